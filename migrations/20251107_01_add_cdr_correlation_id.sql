@@ -0,0 +1,11 @@
+-- Link CDR legs of forwarded/transferred call chains
+-- Migration: 20251107_01
+
+ALTER TABLE call_records ADD COLUMN IF NOT EXISTS correlation_id VARCHAR(255);
+
+-- Index for grouping call chains in per-user call history
+CREATE INDEX IF NOT EXISTS idx_call_records_correlation_id
+    ON call_records(correlation_id)
+    WHERE correlation_id IS NOT NULL;
+
+COMMENT ON COLUMN call_records.correlation_id IS 'Shared by all legs of a forwarded/transferred call chain';
@@ -0,0 +1,30 @@
+-- Personal and company-wide speed dials
+-- Migration: 20251108_01
+
+CREATE TABLE IF NOT EXISTS speed_dials (
+    id UUID PRIMARY KEY,
+    user_id INTEGER REFERENCES users(id) ON DELETE CASCADE,  -- NULL for company-wide codes
+    code VARCHAR(16) NOT NULL,
+    target VARCHAR(255) NOT NULL,
+    label VARCHAR(255),
+    created_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    updated_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+-- One code per user, and one company-wide entry per code
+CREATE UNIQUE INDEX IF NOT EXISTS idx_speed_dials_user_code
+    ON speed_dials(user_id, code)
+    WHERE user_id IS NOT NULL;
+CREATE UNIQUE INDEX IF NOT EXISTS idx_speed_dials_company_code
+    ON speed_dials(code)
+    WHERE user_id IS NULL;
+
+CREATE TRIGGER update_speed_dials_updated_at
+    BEFORE UPDATE ON speed_dials
+    FOR EACH ROW
+    EXECUTE FUNCTION update_updated_at_column();
+
+-- Record the short code a call was dialed with
+ALTER TABLE call_records ADD COLUMN IF NOT EXISTS dialed_number VARCHAR(255);
+
+COMMENT ON COLUMN call_records.dialed_number IS 'Number as dialed when rewritten (e.g. speed dial code); callee_uri holds the resolved destination';
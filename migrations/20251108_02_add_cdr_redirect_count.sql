@@ -0,0 +1,6 @@
+-- Count of 3xx redirects followed to reach the final callee
+-- Migration: 20251108_02
+
+ALTER TABLE call_records ADD COLUMN IF NOT EXISTS redirect_count INTEGER NOT NULL DEFAULT 0;
+
+COMMENT ON COLUMN call_records.redirect_count IS 'Number of 3xx redirects followed; each attempted hop is a separate leg sharing correlation_id';
@@ -0,0 +1,29 @@
+-- Queue events for service level, abandonment and agent occupancy reports
+-- Migration: 20251108_03
+
+CREATE TABLE IF NOT EXISTS queue_events (
+    id UUID PRIMARY KEY,
+    queue_id UUID NOT NULL REFERENCES call_queues(id) ON DELETE CASCADE,
+    call_id VARCHAR(255) NOT NULL,
+    agent_id INTEGER REFERENCES users(id) ON DELETE SET NULL,
+    event_type VARCHAR(20) NOT NULL,  -- QueueEventType
+    occurred_at TIMESTAMPTZ NOT NULL,
+    wait_secs BIGINT NOT NULL DEFAULT 0,
+    talk_secs BIGINT NOT NULL DEFAULT 0,
+    wrap_up_secs BIGINT NOT NULL DEFAULT 0
+);
+
+CREATE INDEX IF NOT EXISTS idx_queue_events_queue_time ON queue_events(queue_id, occurred_at);
+CREATE INDEX IF NOT EXISTS idx_queue_events_agent_time ON queue_events(agent_id, occurred_at) WHERE agent_id IS NOT NULL;
+
+COMMENT ON TABLE queue_events IS 'Steps of queued calls, aggregated into queue and agent reports';
+COMMENT ON COLUMN queue_events.agent_id IS 'Agent user for offered, answered and completed events';
+COMMENT ON COLUMN queue_events.event_type IS 'enqueued, offered, answered, abandoned, overflowed, completed';
+COMMENT ON COLUMN queue_events.occurred_at IS 'Event time; completed events are stamped when the talk ends';
+COMMENT ON COLUMN queue_events.wait_secs IS 'Time in queue for answered, abandoned and overflowed events';
+COMMENT ON COLUMN queue_events.talk_secs IS 'Talk time of completed events';
+COMMENT ON COLUMN queue_events.wrap_up_secs IS 'After-call work following the talk of completed events';
+
+ALTER TABLE call_queues ADD COLUMN IF NOT EXISTS service_level_threshold_secs BIGINT NOT NULL DEFAULT 20;
+
+COMMENT ON COLUMN call_queues.service_level_threshold_secs IS 'Calls answered within this wait (seconds) count towards the service level';
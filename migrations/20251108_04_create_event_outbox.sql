@@ -0,0 +1,25 @@
+-- Outbox of domain events for at-least-once delivery
+-- Migration: 20251108_04
+
+CREATE TABLE IF NOT EXISTS event_outbox (
+    id BIGSERIAL PRIMARY KEY,
+    event_id UUID NOT NULL UNIQUE,
+    aggregate_id UUID NOT NULL,
+    sequence BIGINT NOT NULL,
+    event_type VARCHAR(50) NOT NULL,
+    tenant_id UUID,
+    occurred_at TIMESTAMPTZ NOT NULL,
+    payload JSONB NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    delivered_at TIMESTAMPTZ
+);
+
+CREATE INDEX IF NOT EXISTS idx_event_outbox_undelivered ON event_outbox(id) WHERE delivered_at IS NULL;
+CREATE INDEX IF NOT EXISTS idx_event_outbox_aggregate ON event_outbox(aggregate_id, sequence);
+
+COMMENT ON TABLE event_outbox IS 'Domain events stored before delivery to subscribers, relayed in id order';
+COMMENT ON COLUMN event_outbox.aggregate_id IS 'Aggregate that recorded the event (call id, which is also the CDR id)';
+COMMENT ON COLUMN event_outbox.sequence IS 'Position in the aggregate''s event stream, starting at 1';
+COMMENT ON COLUMN event_outbox.event_type IS 'call.initiated, call.ringing, call.answered, call.held, call.resumed, call.ended';
+COMMENT ON COLUMN event_outbox.payload IS 'Event envelope as JSON';
+COMMENT ON COLUMN event_outbox.delivered_at IS 'Set once the event was handed to subscribers; NULL rows are retried';
@@ -0,0 +1,13 @@
+-- Department and search indexes for the phone directory
+-- Migration: 20251108_05
+
+ALTER TABLE users ADD COLUMN IF NOT EXISTS department VARCHAR(255);
+
+-- Trigram indexes let ILIKE '%text%' searches use an index
+CREATE EXTENSION IF NOT EXISTS pg_trgm;
+
+CREATE INDEX IF NOT EXISTS idx_users_display_name_trgm ON users USING GIN (display_name gin_trgm_ops);
+CREATE INDEX IF NOT EXISTS idx_users_username_trgm ON users USING GIN (username gin_trgm_ops);
+CREATE INDEX IF NOT EXISTS idx_users_department_trgm ON users USING GIN (department gin_trgm_ops);
+
+COMMENT ON COLUMN users.department IS 'Department shown and searched in the phone directory';
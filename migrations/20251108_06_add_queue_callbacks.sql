@@ -0,0 +1,9 @@
+-- Queue callback settings
+-- Migration: 20251108_06
+
+ALTER TABLE call_queues ADD COLUMN IF NOT EXISTS callback_digit CHAR(1);
+ALTER TABLE call_queues ADD COLUMN IF NOT EXISTS callback_max_attempts INTEGER NOT NULL DEFAULT 3;
+ALTER TABLE call_queues ADD COLUMN IF NOT EXISTS callback_retry_delay_secs BIGINT NOT NULL DEFAULT 60;
+
+COMMENT ON COLUMN call_queues.callback_digit IS 'DTMF digit that requests a callback; NULL disables callbacks';
+COMMENT ON COLUMN call_queues.callback_max_attempts IS 'Calls placed to a callback number before it is abandoned';
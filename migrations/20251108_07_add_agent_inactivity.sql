@@ -0,0 +1,10 @@
+-- Queue agent inactivity handling
+-- Migration: 20251108_07
+
+ALTER TABLE call_queues ADD COLUMN IF NOT EXISTS max_missed_offers INTEGER NOT NULL DEFAULT 3;
+ALTER TABLE call_queues ADD COLUMN IF NOT EXISTS inactivity_action VARCHAR(20) NOT NULL DEFAULT 'Pause';
+ALTER TABLE queue_members ADD COLUMN IF NOT EXISTS dnd_linked BOOLEAN NOT NULL DEFAULT FALSE;
+
+COMMENT ON COLUMN call_queues.max_missed_offers IS 'Unanswered offers in a row after which an agent stops receiving calls; 0 disables';
+COMMENT ON COLUMN call_queues.inactivity_action IS 'Pause or LogOut an agent who stops responding';
+COMMENT ON COLUMN queue_members.dnd_linked IS 'Pause the agent in every queue while DND is on';
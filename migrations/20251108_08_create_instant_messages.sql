@@ -0,0 +1,29 @@
+-- Stored SIP MESSAGEs: history and offline store-and-forward
+-- Migration: 20251108_08
+
+CREATE TABLE IF NOT EXISTS instant_messages (
+    id UUID PRIMARY KEY,
+    sender VARCHAR(255) NOT NULL,
+    recipient VARCHAR(255) NOT NULL,
+    content_type VARCHAR(255) NOT NULL DEFAULT 'text/plain',
+    content BYTEA NOT NULL,
+    status VARCHAR(16) NOT NULL DEFAULT 'pending',
+    sent_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    delivered_at TIMESTAMP WITH TIME ZONE,
+    read_at TIMESTAMP WITH TIME ZONE,
+    attempts INTEGER NOT NULL DEFAULT 0,
+    next_attempt_at TIMESTAMP WITH TIME ZONE
+);
+
+-- History of a user, newest first
+CREATE INDEX IF NOT EXISTS idx_instant_messages_sender ON instant_messages(sender, sent_at DESC);
+CREATE INDEX IF NOT EXISTS idx_instant_messages_recipient ON instant_messages(recipient, sent_at DESC);
+-- Store-and-forward queue
+CREATE INDEX IF NOT EXISTS idx_instant_messages_pending
+    ON instant_messages(recipient, sent_at)
+    WHERE status = 'pending';
+
+COMMENT ON COLUMN instant_messages.sender IS 'SIP username of the sender';
+COMMENT ON COLUMN instant_messages.recipient IS 'SIP username of the recipient';
+COMMENT ON COLUMN instant_messages.status IS 'pending, delivered, failed (expired undelivered) or read';
+COMMENT ON COLUMN instant_messages.next_attempt_at IS 'When delivery of a pending message is retried';
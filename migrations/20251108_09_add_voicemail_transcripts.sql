@@ -0,0 +1,11 @@
+-- Voicemail transcripts and the user locale used as language hint
+-- Migration: 20251108_09
+
+ALTER TABLE voicemail_messages ADD COLUMN IF NOT EXISTS transcript TEXT;
+ALTER TABLE voicemail_messages ADD COLUMN IF NOT EXISTS transcript_confidence REAL;
+
+ALTER TABLE users ADD COLUMN IF NOT EXISTS locale VARCHAR(35);
+
+COMMENT ON COLUMN voicemail_messages.transcript IS 'Speech-to-text of the recording, NULL until transcribed';
+COMMENT ON COLUMN voicemail_messages.transcript_confidence IS 'Transcription confidence (0.0 - 1.0)';
+COMMENT ON COLUMN users.locale IS 'Preferred language (BCP 47), used as transcription language hint';
@@ -0,0 +1,8 @@
+-- Time spent on hold per call
+-- Migration: 20251108_10
+
+ALTER TABLE call_records ADD COLUMN IF NOT EXISTS hold_duration INTEGER NOT NULL DEFAULT 0;
+ALTER TABLE call_records ADD COLUMN IF NOT EXISTS hold_count INTEGER NOT NULL DEFAULT 0;
+
+COMMENT ON COLUMN call_records.hold_duration IS 'Seconds on hold, summed over all hold segments';
+COMMENT ON COLUMN call_records.hold_count IS 'Number of hold segments';
@@ -0,0 +1,85 @@
+-- CDR retention: anonymized records and optional monthly partitioning
+-- Migration: 20251108_11
+
+ALTER TABLE call_records ADD COLUMN IF NOT EXISTS anonymized BOOLEAN NOT NULL DEFAULT FALSE;
+
+COMMENT ON COLUMN call_records.anonymized IS 'Parties, addresses and Call-ID removed by retention; kept for aggregates only';
+
+-- Convert call_records into a table partitioned by month of start_time.
+-- Existing rows stay in call_records_unpartitioned, attached as the
+-- partition of everything before next month; retention creates the
+-- monthly partitions from then on. Run once, in a maintenance window:
+--   SELECT partition_call_records();
+CREATE OR REPLACE FUNCTION partition_call_records() RETURNS VOID AS $$
+DECLARE
+    next_month DATE := (date_trunc('month', CURRENT_DATE) + INTERVAL '1 month')::DATE;
+    index_name TEXT;
+BEGIN
+    IF EXISTS (SELECT 1 FROM pg_partitioned_table WHERE partrelid = 'call_records'::regclass) THEN
+        RETURN;
+    END IF;
+
+    ALTER TABLE call_records RENAME TO call_records_unpartitioned;
+    ALTER TABLE call_records_unpartitioned
+        RENAME CONSTRAINT call_records_pkey TO call_records_unpartitioned_pkey;
+    DROP TRIGGER IF EXISTS update_call_records_updated_at ON call_records_unpartitioned;
+    FOR index_name IN
+        SELECT indexname FROM pg_indexes
+        WHERE tablename = 'call_records_unpartitioned' AND indexname LIKE 'idx_call_records_%'
+    LOOP
+        EXECUTE format('DROP INDEX %I', index_name);
+    END LOOP;
+
+    CREATE TABLE call_records (
+        LIKE call_records_unpartitioned INCLUDING DEFAULTS INCLUDING CONSTRAINTS,
+        PRIMARY KEY (id, start_time)
+    ) PARTITION BY RANGE (start_time);
+
+    ALTER TABLE call_records ATTACH PARTITION call_records_unpartitioned
+        FOR VALUES FROM (MINVALUE) TO (next_month);
+
+    CREATE INDEX idx_call_records_call_id ON call_records(call_id);
+    CREATE INDEX idx_call_records_caller_username ON call_records(caller_username);
+    CREATE INDEX idx_call_records_callee_username ON call_records(callee_username);
+    CREATE INDEX idx_call_records_start_time ON call_records(start_time DESC);
+    CREATE INDEX idx_call_records_status ON call_records(status);
+    CREATE INDEX idx_call_records_direction ON call_records(direction);
+    CREATE INDEX idx_call_records_caller_time ON call_records(caller_username, start_time DESC);
+    CREATE INDEX idx_call_records_callee_time ON call_records(callee_username, start_time DESC);
+    CREATE INDEX idx_call_records_time_status ON call_records(start_time DESC, status);
+    CREATE INDEX idx_call_records_correlation_id ON call_records(correlation_id)
+        WHERE correlation_id IS NOT NULL;
+
+    CREATE TRIGGER update_call_records_updated_at BEFORE UPDATE ON call_records
+        FOR EACH ROW EXECUTE FUNCTION update_updated_at_column();
+END;
+$$ LANGUAGE plpgsql;
+
+-- Create the partition of the month starting at `month`. Returns its name,
+-- or NULL when call_records is not partitioned, the partition exists or the
+-- month is still covered by call_records_unpartitioned.
+CREATE OR REPLACE FUNCTION create_call_records_partition(month DATE) RETURNS TEXT AS $$
+DECLARE
+    month_start DATE := date_trunc('month', month)::DATE;
+    partition_name TEXT := 'call_records_' || to_char(month, 'YYYY_MM');
+BEGIN
+    IF NOT EXISTS (SELECT 1 FROM pg_partitioned_table WHERE partrelid = 'call_records'::regclass)
+        OR to_regclass(partition_name) IS NOT NULL
+    THEN
+        RETURN NULL;
+    END IF;
+
+    BEGIN
+        EXECUTE format(
+            'CREATE TABLE %I PARTITION OF call_records FOR VALUES FROM (%L) TO (%L)',
+            partition_name,
+            month_start,
+            (month_start + INTERVAL '1 month')::DATE
+        );
+    EXCEPTION WHEN invalid_object_definition THEN
+        -- Overlaps an existing partition
+        RETURN NULL;
+    END;
+    RETURN partition_name;
+END;
+$$ LANGUAGE plpgsql;
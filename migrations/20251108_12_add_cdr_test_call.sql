@@ -0,0 +1,6 @@
+-- Calls to built-in test services (echo, milliwatt, readback)
+-- Migration: 20251108_12
+
+ALTER TABLE call_records ADD COLUMN IF NOT EXISTS test_call BOOLEAN NOT NULL DEFAULT FALSE;
+
+COMMENT ON COLUMN call_records.test_call IS 'Call to a built-in test service; excluded from billing';
@@ -0,0 +1,11 @@
+-- Queue overflow rules (e.g. to an answering service)
+-- Migration: 20251108_13
+
+ALTER TABLE call_queues ADD COLUMN IF NOT EXISTS overflow_rules JSONB NOT NULL DEFAULT '[]';
+
+COMMENT ON COLUMN call_queues.overflow_rules IS 'Conditions and destinations sending calls elsewhere, checked in order on arrival and while waiting';
+
+-- Calls sent on by an overflow rule keep their own disposition
+ALTER TABLE call_records DROP CONSTRAINT IF EXISTS call_records_status_check;
+ALTER TABLE call_records ADD CONSTRAINT call_records_status_check
+    CHECK (status IN ('active', 'completed', 'failed', 'busy', 'no_answer', 'cancelled', 'rejected', 'overflowed'));
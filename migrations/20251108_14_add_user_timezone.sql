@@ -0,0 +1,6 @@
+-- User time zone for user-scoped API responses
+-- Migration: 20251108_14
+
+ALTER TABLE users ADD COLUMN IF NOT EXISTS timezone VARCHAR(64);
+
+COMMENT ON COLUMN users.timezone IS 'IANA time zone (e.g. Europe/Berlin) of call history and other user-scoped responses; NULL uses the tenant default';
@@ -0,0 +1,30 @@
+-- Announcement-only numbers (info lines) with time-of-day messages
+-- Migration: 20251108_15
+
+CREATE TABLE IF NOT EXISTS announcement_routes (
+    id UUID PRIMARY KEY,
+    tenant VARCHAR(255) NOT NULL,
+    number VARCHAR(64) NOT NULL,
+    messages JSONB NOT NULL DEFAULT '[]',
+    default_audio_file VARCHAR(64) NOT NULL,
+    repeat_count INTEGER NOT NULL DEFAULT 1 CHECK (repeat_count BETWEEN 1 AND 10),
+    follow_up JSONB NOT NULL DEFAULT '{"action": "hangup"}',
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    updated_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    UNIQUE (tenant, number)
+);
+
+COMMENT ON TABLE announcement_routes IS 'Numbers that answer, play a message chosen by time of day and then hang up, go to voicemail or forward';
+COMMENT ON COLUMN announcement_routes.tenant IS 'SIP realm the number belongs to; its time zone decides the message';
+COMMENT ON COLUMN announcement_routes.messages IS 'Time ranges and audio library files, checked in order';
+COMMENT ON COLUMN announcement_routes.default_audio_file IS 'Audio library file played outside every range';
+COMMENT ON COLUMN announcement_routes.follow_up IS 'hangup, voicemail (mailbox) or forward (target) after the message';
+
+-- Calls answered by an announcement-only number
+ALTER TABLE call_records ADD COLUMN IF NOT EXISTS announcement_variant VARCHAR(16);
+
+COMMENT ON COLUMN call_records.announcement_variant IS 'Message played by an announcement-only number: 1-based position of its time range, or default';
+
+ALTER TABLE call_records DROP CONSTRAINT IF EXISTS call_records_status_check;
+ALTER TABLE call_records ADD CONSTRAINT call_records_status_check
+    CHECK (status IN ('active', 'completed', 'failed', 'busy', 'no_answer', 'cancelled', 'rejected', 'overflowed', 'announcement'));
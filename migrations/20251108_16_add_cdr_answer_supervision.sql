@@ -0,0 +1,16 @@
+-- Answer supervision: billing timestamps and suspect answers
+-- Migration: 20251108_16
+
+ALTER TABLE call_records ADD COLUMN IF NOT EXISTS early_media_time TIMESTAMPTZ;
+ALTER TABLE call_records ADD COLUMN IF NOT EXISTS ack_time TIMESTAMPTZ;
+ALTER TABLE call_records ADD COLUMN IF NOT EXISTS trunk VARCHAR(64);
+ALTER TABLE call_records ADD COLUMN IF NOT EXISTS suspect_answer BOOLEAN NOT NULL DEFAULT FALSE;
+
+COMMENT ON COLUMN call_records.early_media_time IS 'First early media (183 with SDP) from the callee; never billed from';
+COMMENT ON COLUMN call_records.ack_time IS 'ACK of the 2xx completing the answer';
+COMMENT ON COLUMN call_records.trunk IS 'Trunk the call came in from or went out through';
+COMMENT ON COLUMN call_records.suspect_answer IS 'Hung up within the trunk answer validation window';
+
+CREATE INDEX IF NOT EXISTS idx_call_records_suspect_answer
+    ON call_records (start_time)
+    WHERE suspect_answer;
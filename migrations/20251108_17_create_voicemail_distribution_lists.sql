@@ -0,0 +1,26 @@
+-- Voicemail distribution lists for group announcements
+-- Migration: 20251108_17
+
+CREATE TABLE IF NOT EXISTS voicemail_distribution_lists (
+    id UUID PRIMARY KEY,
+    name VARCHAR(255) NOT NULL,
+    number VARCHAR(32) NOT NULL UNIQUE,
+    owner_mailbox VARCHAR(255) NOT NULL,
+    members TEXT[] NOT NULL DEFAULT '{}',
+    max_members INTEGER NOT NULL DEFAULT 50 CHECK (max_members BETWEEN 1 AND 500),
+    senders JSONB NOT NULL DEFAULT '{"scope": "owner"}',
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    updated_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+COMMENT ON TABLE voicemail_distribution_lists IS 'Groups of mailboxes that each get a copy of a message sent to the list number';
+COMMENT ON COLUMN voicemail_distribution_lists.number IS 'Number entered in the voicemail menu to address the list';
+COMMENT ON COLUMN voicemail_distribution_lists.members IS 'Member mailbox ids, read when a message is sent';
+COMMENT ON COLUMN voicemail_distribution_lists.senders IS 'owner, members, everyone or mailboxes (list) allowed to send besides the owner';
+
+-- Copies of a list message share one recording
+ALTER TABLE voicemail_messages ADD COLUMN IF NOT EXISTS via_list VARCHAR(255);
+
+CREATE INDEX IF NOT EXISTS idx_voicemail_messages_audio_file_path ON voicemail_messages(audio_file_path);
+
+COMMENT ON COLUMN voicemail_messages.via_list IS 'Name of the distribution list the message was sent to';
@@ -0,0 +1,31 @@
+-- Auto-attendants with extension dialing and dial-by-name
+-- Migration: 20251108_18
+
+CREATE TABLE IF NOT EXISTS auto_attendants (
+    id UUID PRIMARY KEY,
+    tenant VARCHAR(255) NOT NULL,
+    name VARCHAR(255) NOT NULL,
+    numbers JSONB NOT NULL DEFAULT '[]',
+    day_greeting VARCHAR(255) NOT NULL,
+    night_greeting VARCHAR(255),
+    business_hours JSONB NOT NULL DEFAULT '[]',
+    options JSONB NOT NULL DEFAULT '[]',
+    extension_dialing JSONB,
+    dial_by_name JSONB NOT NULL DEFAULT '{}',
+    max_retries INTEGER NOT NULL DEFAULT 3 CHECK (max_retries >= 1),
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    updated_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+CREATE INDEX IF NOT EXISTS idx_auto_attendants_tenant ON auto_attendants(tenant);
+CREATE INDEX IF NOT EXISTS idx_auto_attendants_numbers ON auto_attendants USING GIN (numbers);
+
+COMMENT ON TABLE auto_attendants IS 'Reception menus answering DIDs: menu keys, extension dialing and dial-by-name';
+COMMENT ON COLUMN auto_attendants.numbers IS 'Numbers answered, each always or only outside business hours (after_hours)';
+COMMENT ON COLUMN auto_attendants.night_greeting IS 'Greeting outside business_hours; the day greeting if NULL';
+COMMENT ON COLUMN auto_attendants.extension_dialing IS 'Extension collection settings; NULL disables extension dialing';
+
+-- Recorded names read back by dial-by-name
+ALTER TABLE voicemail_mailboxes ADD COLUMN IF NOT EXISTS name_greeting_file VARCHAR(255);
+
+COMMENT ON COLUMN voicemail_mailboxes.name_greeting_file IS 'Recorded name of the mailbox owner, read back by dial-by-name directories';
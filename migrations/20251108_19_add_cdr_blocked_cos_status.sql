@@ -0,0 +1,6 @@
+-- Calls refused by the caller's class of service
+-- Migration: 20251108_19
+
+ALTER TABLE call_records DROP CONSTRAINT IF EXISTS call_records_status_check;
+ALTER TABLE call_records ADD CONSTRAINT call_records_status_check
+    CHECK (status IN ('active', 'completed', 'failed', 'busy', 'no_answer', 'cancelled', 'rejected', 'overflowed', 'announcement', 'blocked-cos'));
@@ -0,0 +1,6 @@
+-- Calls captioned live by the transcription hook
+-- Migration: 20251108_20
+
+ALTER TABLE call_records ADD COLUMN IF NOT EXISTS live_transcribed BOOLEAN NOT NULL DEFAULT FALSE;
+
+COMMENT ON COLUMN call_records.live_transcribed IS 'Audio was sent to the transcription provider for live captions';
@@ -0,0 +1,25 @@
+-- Call aggregates, saved on every state change
+-- Migration: 20251108_21
+
+CREATE TABLE IF NOT EXISTS calls (
+    id UUID PRIMARY KEY,
+    state VARCHAR(20) NOT NULL,
+    active BOOLEAN NOT NULL,
+    caller_aor VARCHAR(512) NOT NULL,
+    callee_aor VARCHAR(512) NOT NULL,
+    caller_endpoint UUID NOT NULL,
+    callee_endpoint UUID NOT NULL,
+    aggregate JSONB NOT NULL,
+    version BIGINT NOT NULL CHECK (version >= 1),
+    started_at TIMESTAMPTZ NOT NULL,
+    updated_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+CREATE INDEX IF NOT EXISTS idx_calls_active_caller ON calls(caller_aor) WHERE active;
+CREATE INDEX IF NOT EXISTS idx_calls_active_callee ON calls(callee_aor) WHERE active;
+CREATE INDEX IF NOT EXISTS idx_calls_caller_endpoint ON calls(caller_endpoint);
+CREATE INDEX IF NOT EXISTS idx_calls_callee_endpoint ON calls(callee_endpoint);
+
+COMMENT ON TABLE calls IS 'Call aggregates; the id is shared with the call''s CDR';
+COMMENT ON COLUMN calls.aggregate IS 'Serialized aggregate; state, active and the participant columns are copies for lookups';
+COMMENT ON COLUMN calls.version IS 'Bumped on every save; a save expecting another version is refused';
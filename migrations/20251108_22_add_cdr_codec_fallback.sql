@@ -0,0 +1,6 @@
+-- Calls whose media fell back from Opus to G.711 mid-call
+-- Migration: 20251108_22
+
+ALTER TABLE call_records ADD COLUMN IF NOT EXISTS codec_fallback TEXT;
+
+COMMENT ON COLUMN call_records.codec_fallback IS 'Why media fell back from Opus to G.711 mid-call (decode error rate, codec and leg)';
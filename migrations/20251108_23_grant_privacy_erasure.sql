@@ -0,0 +1,7 @@
+-- Administrators may run erasure requests
+-- Migration: 20251108_23
+
+UPDATE roles
+SET permissions = array_append(permissions, 'privacy:erasure')
+WHERE id = 'a0000000-0000-0000-0000-000000000001'::uuid
+  AND NOT ('privacy:erasure' = ANY(permissions));
@@ -0,0 +1,17 @@
+-- Responses of API requests sent with an Idempotency-Key, for replay
+-- Migration: 20251108_24
+
+CREATE TABLE IF NOT EXISTS idempotency_keys (
+    key VARCHAR(255) PRIMARY KEY,
+    fingerprint VARCHAR(64) NOT NULL,
+    status SMALLINT NOT NULL,
+    content_type VARCHAR(255),
+    body BYTEA NOT NULL,
+    expires_at TIMESTAMPTZ NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+CREATE INDEX IF NOT EXISTS idx_idempotency_keys_expires_at ON idempotency_keys(expires_at);
+
+COMMENT ON TABLE idempotency_keys IS 'Responses replayed to retries of the same request until they expire';
+COMMENT ON COLUMN idempotency_keys.fingerprint IS 'SHA-256 of method, path and body; the key on another request is refused';
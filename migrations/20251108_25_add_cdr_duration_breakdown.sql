@@ -0,0 +1,11 @@
+-- Setup, ring and talk time of calls
+-- Migration: 20251108_25
+
+ALTER TABLE call_records ADD COLUMN IF NOT EXISTS ring_time TIMESTAMPTZ;
+ALTER TABLE call_records ADD COLUMN IF NOT EXISTS ring_duration INTEGER;
+ALTER TABLE call_records ADD COLUMN IF NOT EXISTS talk_duration INTEGER;
+
+COMMENT ON COLUMN call_records.setup_duration IS 'Setup duration in seconds (first 180/183, or answer_time when there was none, - start_time)';
+COMMENT ON COLUMN call_records.ring_time IS 'First provisional response (180 or 183) from the callee';
+COMMENT ON COLUMN call_records.ring_duration IS 'Seconds from ring_time to the answer, or to the end of a call never answered';
+COMMENT ON COLUMN call_records.talk_duration IS 'Seconds answered and not on hold (call_duration - hold_duration); 0 for calls never answered';
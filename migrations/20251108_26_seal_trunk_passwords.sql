@@ -0,0 +1,15 @@
+-- Encrypted trunk passwords
+-- Migration: 20251108_26
+
+-- Sealed passwords are longer than the plaintext
+ALTER TABLE sip_trunks ALTER COLUMN password TYPE TEXT;
+
+-- Mark the passwords stored so far as plaintext; `yakyak rotate-secrets`
+-- seals them with the configured key
+UPDATE sip_trunks
+SET password = 'plain:' || password
+WHERE password IS NOT NULL
+  AND password NOT LIKE 'enc:v1:%'
+  AND password NOT LIKE 'plain:%';
+
+COMMENT ON COLUMN sip_trunks.password IS 'enc:v1:<key id>:<base64 nonce and AES-256-GCM ciphertext>, or plain:<password> until sealed';
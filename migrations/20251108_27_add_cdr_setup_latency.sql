@@ -0,0 +1,12 @@
+-- Call setup latency of calls
+-- Migration: 20251108_27
+
+ALTER TABLE call_records ADD COLUMN IF NOT EXISTS auth_latency_ms INTEGER;
+ALTER TABLE call_records ADD COLUMN IF NOT EXISTS routing_latency_ms INTEGER;
+ALTER TABLE call_records ADD COLUMN IF NOT EXISTS provisional_latency_ms INTEGER;
+ALTER TABLE call_records ADD COLUMN IF NOT EXISTS answer_latency_ms INTEGER;
+
+COMMENT ON COLUMN call_records.auth_latency_ms IS 'Milliseconds from the INVITE to the caller authenticated';
+COMMENT ON COLUMN call_records.routing_latency_ms IS 'Milliseconds from the INVITE to the routing decision';
+COMMENT ON COLUMN call_records.provisional_latency_ms IS 'Milliseconds from the INVITE to the first 180/183 sent';
+COMMENT ON COLUMN call_records.answer_latency_ms IS 'Milliseconds from the INVITE to the answer';
@@ -0,0 +1,30 @@
+-- Softphone enrollment tokens and the device credentials they create
+-- Migration: 20251108_28
+
+CREATE TABLE IF NOT EXISTS device_enrollments (
+    id UUID PRIMARY KEY,
+    user_id INTEGER NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    token_hash VARCHAR(64) NOT NULL UNIQUE,
+    label VARCHAR(255),
+    max_uses INTEGER NOT NULL CHECK (max_uses >= 1),
+    uses INTEGER NOT NULL DEFAULT 0,
+    expires_at TIMESTAMPTZ NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+CREATE INDEX IF NOT EXISTS idx_device_enrollments_user_id ON device_enrollments(user_id);
+
+CREATE TABLE IF NOT EXISTS device_credentials (
+    id UUID PRIMARY KEY,
+    user_id INTEGER NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    enrollment_id UUID REFERENCES device_enrollments(id) ON DELETE SET NULL,
+    label VARCHAR(255),
+    sip_ha1 VARCHAR(32) NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    revoked_at TIMESTAMPTZ
+);
+
+CREATE INDEX IF NOT EXISTS idx_device_credentials_user_id ON device_credentials(user_id);
+
+COMMENT ON COLUMN device_enrollments.token_hash IS 'Hex SHA-256 of the token; the token itself is only shown when created';
+COMMENT ON TABLE device_credentials IS 'Extra SIP passwords of enrolled devices, accepted besides the user''s own';
@@ -0,0 +1,28 @@
+-- Wake-up calls and reminders placed at a local time
+-- Migration: 20251108_29
+
+CREATE TABLE IF NOT EXISTS scheduled_calls (
+    id UUID PRIMARY KEY,
+    target VARCHAR(255) NOT NULL,
+    realm VARCHAR(255),
+    local_time TIMESTAMP NOT NULL,
+    time_zone VARCHAR(64) NOT NULL,
+    recurrence VARCHAR(16),
+    content JSONB NOT NULL,
+    confirm_digit VARCHAR(1),
+    retry JSONB NOT NULL,
+    notify_email VARCHAR(255),
+    created_by VARCHAR(255) NOT NULL,
+    status VARCHAR(16) NOT NULL DEFAULT 'pending',
+    next_attempt_at TIMESTAMPTZ NOT NULL,
+    attempts INTEGER NOT NULL DEFAULT 0,
+    last_result VARCHAR(255),
+    last_outcome VARCHAR(16),
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    updated_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+CREATE INDEX IF NOT EXISTS idx_scheduled_calls_due ON scheduled_calls(status, next_attempt_at);
+
+COMMENT ON COLUMN scheduled_calls.local_time IS 'Local time of the current occurrence in time_zone';
+COMMENT ON COLUMN scheduled_calls.next_attempt_at IS 'When the next attempt is due, retries included';
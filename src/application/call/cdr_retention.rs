@@ -0,0 +1,478 @@
+//! Scheduled and on-demand CDR retention
+//!
+//! A run creates the upcoming monthly partitions (when partitioning is on),
+//! then walks the CDRs old enough to have expired under some policy, oldest
+//! first, in batches. Each batch is archived before it is changed, then
+//! anonymized and deleted as the CDR's tenant policy says; the task pauses
+//! between batches so it never holds the pool for long.
+
+use crate::domain::cdr::CallDetailRecord;
+use crate::domain::cdr_retention::{
+    CdrRetentionConfig, CdrRetentionReport, CdrRetentionRepository, RetentionAction,
+};
+use chrono::{DateTime, Datelike, Months, NaiveDate, Utc};
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use metrics::{counter, histogram};
+use serde::Serialize;
+use std::io::{self, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::sync::Mutex;
+use tokio::task::JoinHandle;
+use tracing::{error, info};
+use uuid::Uuid;
+
+/// One line of a retention archive
+#[derive(Serialize)]
+struct ArchivedCdr<'a> {
+    retention_action: RetentionAction,
+    #[serde(flatten)]
+    cdr: &'a CallDetailRecord,
+}
+
+/// Write the batch to `path` as gzip-compressed JSON lines
+fn write_archive(path: &Path, records: &[(RetentionAction, CallDetailRecord)]) -> io::Result<()> {
+    let file = std::fs::File::create(path)?;
+    let mut writer = BufWriter::new(GzEncoder::new(file, Compression::default()));
+    for (retention_action, cdr) in records {
+        let line = ArchivedCdr {
+            retention_action: *retention_action,
+            cdr,
+        };
+        serde_json::to_writer(&mut writer, &line)?;
+        writer.write_all(b"\n")?;
+    }
+    writer
+        .into_inner()
+        .map_err(|e| e.into_error())?
+        .finish()?
+        .sync_all()
+}
+
+pub struct CdrRetentionService {
+    config: CdrRetentionConfig,
+    repository: Arc<dyn CdrRetentionRepository>,
+    /// Held for the duration of a run; runs never overlap
+    running: Mutex<()>,
+}
+
+impl CdrRetentionService {
+    pub fn new(config: CdrRetentionConfig, repository: Arc<dyn CdrRetentionRepository>) -> Self {
+        Self {
+            config,
+            repository,
+            running: Mutex::new(()),
+        }
+    }
+
+    pub fn config(&self) -> &CdrRetentionConfig {
+        &self.config
+    }
+
+    /// Apply retention now; a dry run only counts what would change
+    pub async fn run(&self, dry_run: bool) -> Result<CdrRetentionReport, String> {
+        self.run_at(Utc::now(), dry_run).await
+    }
+
+    /// Apply retention as of `now`
+    pub async fn run_at(
+        &self,
+        now: DateTime<Utc>,
+        dry_run: bool,
+    ) -> Result<CdrRetentionReport, String> {
+        let _running = self
+            .running
+            .try_lock()
+            .map_err(|_| "CDR retention is already running".to_string())?;
+        let started = Instant::now();
+        let mut report = CdrRetentionReport {
+            dry_run,
+            ..Default::default()
+        };
+
+        if self.config.partitioning.enabled && !dry_run {
+            self.create_partitions(now, &mut report).await?;
+        }
+
+        let cutoff = self.config.earliest_cutoff(now);
+        let batch_size = self.config.batch_size.max(1);
+        let mut after: Option<(DateTime<Utc>, Uuid)> = None;
+        let mut batch_number = 0;
+        loop {
+            let candidates = self
+                .repository
+                .list_started_before(cutoff, after, batch_size as i64)
+                .await?;
+            let Some(last) = candidates.last() else {
+                break;
+            };
+            after = Some((last.cdr.start_time, last.cdr.id));
+            report.examined += candidates.len() as u64;
+            let exhausted = candidates.len() < batch_size as usize;
+
+            let expiring: Vec<(RetentionAction, CallDetailRecord)> = candidates
+                .into_iter()
+                .filter_map(|candidate| {
+                    match self.config.policy_for(&candidate.cdr).action(
+                        candidate.cdr.start_time,
+                        candidate.anonymized,
+                        now,
+                    ) {
+                        RetentionAction::Keep => None,
+                        action => Some((action, candidate.cdr)),
+                    }
+                })
+                .collect();
+            let ids = |wanted: RetentionAction| -> Vec<Uuid> {
+                expiring
+                    .iter()
+                    .filter(|(action, _)| *action == wanted)
+                    .map(|(_, cdr)| cdr.id)
+                    .collect()
+            };
+            let to_anonymize = ids(RetentionAction::Anonymize);
+            let to_delete = ids(RetentionAction::Delete);
+
+            if dry_run {
+                report.anonymized += to_anonymize.len() as u64;
+                report.deleted += to_delete.len() as u64;
+            } else if !expiring.is_empty() {
+                batch_number += 1;
+                if let Some(path) = self.archive(now, batch_number, expiring).await? {
+                    report.archives.push(path);
+                }
+                if !to_anonymize.is_empty() {
+                    let anonymized = self.repository.anonymize(&to_anonymize).await?;
+                    counter!("cdr_retention_rows_purged_total", "action" => "anonymized")
+                        .increment(anonymized);
+                    report.anonymized += anonymized;
+                }
+                if !to_delete.is_empty() {
+                    let deleted = self.repository.delete(&to_delete).await?;
+                    counter!("cdr_retention_rows_purged_total", "action" => "deleted")
+                        .increment(deleted);
+                    report.deleted += deleted;
+                }
+            }
+
+            if exhausted {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(self.config.batch_pause_ms)).await;
+        }
+
+        let elapsed = started.elapsed();
+        report.duration_ms = elapsed.as_millis() as u64;
+        if !dry_run {
+            histogram!("cdr_retention_duration_seconds").record(elapsed.as_secs_f64());
+        }
+        info!(
+            "CDR retention{}: examined {}, anonymized {}, deleted {} in {} ms",
+            if dry_run { " (dry run)" } else { "" },
+            report.examined,
+            report.anonymized,
+            report.deleted,
+            report.duration_ms
+        );
+        Ok(report)
+    }
+
+    /// Partitions of the current month and `months_ahead` following ones
+    async fn create_partitions(
+        &self,
+        now: DateTime<Utc>,
+        report: &mut CdrRetentionReport,
+    ) -> Result<(), String> {
+        let today = now.date_naive();
+        let month = NaiveDate::from_ymd_opt(today.year(), today.month(), 1)
+            .ok_or_else(|| format!("Invalid month of {}", today))?;
+        for ahead in 0..=self.config.partitioning.months_ahead {
+            let Some(start) = month.checked_add_months(Months::new(ahead)) else {
+                break;
+            };
+            if let Some(partition) = self.repository.ensure_partition(start).await? {
+                info!("Created CDR partition {}", partition);
+                report.partitions_created.push(partition);
+            }
+        }
+        Ok(())
+    }
+
+    /// Archive a batch before it is changed; returns the file written
+    async fn archive(
+        &self,
+        now: DateTime<Utc>,
+        batch_number: u32,
+        records: Vec<(RetentionAction, CallDetailRecord)>,
+    ) -> Result<Option<String>, String> {
+        let Some(dir) = &self.config.archive_dir else {
+            return Ok(None);
+        };
+        let path = PathBuf::from(dir).join(format!(
+            "cdr-retention-{}-{:04}.jsonl.gz",
+            now.format("%Y%m%dT%H%M%SZ"),
+            batch_number
+        ));
+        let file = path.clone();
+        tokio::task::spawn_blocking(move || {
+            if let Some(parent) = file.parent() {
+                std::fs::create_dir_all(parent)?;
+            }
+            write_archive(&file, &records)
+        })
+        .await
+        .map_err(|e| format!("Archive task failed: {}", e))?
+        .map_err(|e| format!("Failed to write CDR archive {}: {}", path.display(), e))?;
+        Ok(Some(path.display().to_string()))
+    }
+}
+
+/// Run retention every `interval_secs` while it is enabled
+pub fn spawn_cdr_retention(service: Arc<CdrRetentionService>) -> JoinHandle<()> {
+    tokio::spawn(async move {
+        let interval = Duration::from_secs(service.config.interval_secs.max(1));
+        loop {
+            tokio::time::sleep(interval).await;
+            if let Err(e) = service.run(false).await {
+                error!("CDR retention failed: {}", e);
+            }
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::domain::cdr::CallDirection;
+    use crate::domain::cdr_retention::{RetentionCandidate, RetentionPolicy};
+    use async_trait::async_trait;
+    use flate2::read::GzDecoder;
+    use std::io::{BufRead, BufReader};
+    use std::sync::Mutex as StdMutex;
+
+    /// CDRs with their anonymized flag
+    #[derive(Default)]
+    struct InMemoryRetention {
+        records: StdMutex<Vec<(CallDetailRecord, bool)>>,
+        batches: StdMutex<usize>,
+    }
+
+    impl InMemoryRetention {
+        fn seed(&self, caller: &str, started: DateTime<Utc>) -> Uuid {
+            let mut cdr = CallDetailRecord::new(
+                format!("call-{}", Uuid::new_v4()),
+                "alice".to_string(),
+                caller.to_string(),
+                "192.0.2.1".to_string(),
+                "bob".to_string(),
+                "sip:bob@other.example.com".to_string(),
+                CallDirection::Internal,
+            );
+            cdr.start_time = started;
+            let id = cdr.id;
+            self.records.lock().unwrap().push((cdr, false));
+            id
+        }
+
+        fn find(&self, id: Uuid) -> Option<(CallDetailRecord, bool)> {
+            self.records
+                .lock()
+                .unwrap()
+                .iter()
+                .find(|(cdr, _)| cdr.id == id)
+                .cloned()
+        }
+    }
+
+    #[async_trait]
+    impl CdrRetentionRepository for InMemoryRetention {
+        async fn list_started_before(
+            &self,
+            before: DateTime<Utc>,
+            after: Option<(DateTime<Utc>, Uuid)>,
+            limit: i64,
+        ) -> Result<Vec<RetentionCandidate>, String> {
+            *self.batches.lock().unwrap() += 1;
+            let mut records: Vec<_> = self
+                .records
+                .lock()
+                .unwrap()
+                .iter()
+                .filter(|(cdr, _)| cdr.start_time < before)
+                .filter(|(cdr, _)| after.is_none_or(|after| (cdr.start_time, cdr.id) > after))
+                .cloned()
+                .collect();
+            records.sort_by_key(|(cdr, _)| (cdr.start_time, cdr.id));
+            Ok(records
+                .into_iter()
+                .take(limit as usize)
+                .map(|(cdr, anonymized)| RetentionCandidate { cdr, anonymized })
+                .collect())
+        }
+
+        async fn delete(&self, ids: &[Uuid]) -> Result<u64, String> {
+            let mut records = self.records.lock().unwrap();
+            let before = records.len();
+            records.retain(|(cdr, _)| !ids.contains(&cdr.id));
+            Ok((before - records.len()) as u64)
+        }
+
+        async fn anonymize(&self, ids: &[Uuid]) -> Result<u64, String> {
+            let mut count = 0;
+            for (cdr, anonymized) in self.records.lock().unwrap().iter_mut() {
+                if ids.contains(&cdr.id) {
+                    cdr.caller_username = "anonymous".to_string();
+                    *anonymized = true;
+                    count += 1;
+                }
+            }
+            Ok(count)
+        }
+
+        async fn ensure_partition(&self, month: NaiveDate) -> Result<Option<String>, String> {
+            Ok(Some(format!("call_records_{}", month.format("%Y_%m"))))
+        }
+    }
+
+    fn config() -> CdrRetentionConfig {
+        let mut config = CdrRetentionConfig {
+            batch_pause_ms: 0,
+            default_policy: RetentionPolicy {
+                full_days: 30,
+                aggregate_days: 0,
+            },
+            ..Default::default()
+        };
+        config.tenants.insert(
+            "acme.example.com".to_string(),
+            RetentionPolicy {
+                full_days: 30,
+                aggregate_days: 90,
+            },
+        );
+        config
+    }
+
+    fn days_ago(now: DateTime<Utc>, days: i64) -> DateTime<Utc> {
+        now - chrono::Duration::days(days)
+    }
+
+    fn read_archive(path: &str) -> Vec<serde_json::Value> {
+        let file = std::fs::File::open(path).unwrap();
+        BufReader::new(GzDecoder::new(file))
+            .lines()
+            .map(|line| serde_json::from_str(&line.unwrap()).unwrap())
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn test_policy_boundary_and_archive() {
+        let now = Utc::now();
+        let repository = Arc::new(InMemoryRetention::default());
+        let recent = repository.seed("sip:alice@other.example.com", days_ago(now, 29));
+        let expired = repository.seed("sip:alice@other.example.com", days_ago(now, 31));
+        let tenant_recent = repository.seed("sip:alice@acme.example.com", days_ago(now, 29));
+        let tenant_aggregate = repository.seed("sip:alice@acme.example.com", days_ago(now, 31));
+        let tenant_expired = repository.seed("sip:alice@acme.example.com", days_ago(now, 100));
+
+        let archive_dir = std::env::temp_dir().join(format!("yakyak-retention-{}", Uuid::new_v4()));
+        let mut config = config();
+        config.archive_dir = Some(archive_dir.display().to_string());
+        let service = CdrRetentionService::new(config, repository.clone());
+
+        let report = service.run_at(now, false).await.unwrap();
+        assert_eq!(report.examined, 3);
+        assert_eq!(report.deleted, 2);
+        assert_eq!(report.anonymized, 1);
+
+        assert!(repository.find(recent).is_some());
+        assert!(repository.find(tenant_recent).is_some());
+        assert!(repository.find(expired).is_none());
+        assert!(repository.find(tenant_expired).is_none());
+        let (anonymized, flag) = repository.find(tenant_aggregate).unwrap();
+        assert!(flag);
+        assert_eq!(anonymized.caller_username, "anonymous");
+
+        // The archive has the records as they were before the run
+        assert_eq!(report.archives.len(), 1);
+        let lines = read_archive(&report.archives[0]);
+        assert_eq!(lines.len(), 3);
+        let line_of = |id: Uuid| {
+            lines
+                .iter()
+                .find(|line| line["id"] == id.to_string())
+                .unwrap()
+                .clone()
+        };
+        assert_eq!(line_of(expired)["retention_action"], "delete");
+        assert_eq!(line_of(tenant_expired)["retention_action"], "delete");
+        let aggregate = line_of(tenant_aggregate);
+        assert_eq!(aggregate["retention_action"], "anonymize");
+        assert_eq!(aggregate["caller_username"], "alice");
+        assert_eq!(aggregate["caller_uri"], "sip:alice@acme.example.com");
+
+        // Anonymized CDRs are kept until their aggregate period ends
+        let report = service.run_at(now, false).await.unwrap();
+        assert_eq!((report.deleted, report.anonymized), (0, 0));
+        assert!(report.archives.is_empty());
+
+        std::fs::remove_dir_all(archive_dir).ok();
+    }
+
+    #[tokio::test]
+    async fn test_dry_run_changes_nothing() {
+        let now = Utc::now();
+        let repository = Arc::new(InMemoryRetention::default());
+        let expired = repository.seed("sip:alice@other.example.com", days_ago(now, 31));
+        let tenant_aggregate = repository.seed("sip:alice@acme.example.com", days_ago(now, 31));
+
+        let archive_dir = std::env::temp_dir().join(format!("yakyak-retention-{}", Uuid::new_v4()));
+        let mut config = config();
+        config.archive_dir = Some(archive_dir.display().to_string());
+        config.partitioning.enabled = true;
+        let service = CdrRetentionService::new(config, repository.clone());
+
+        let report = service.run_at(now, true).await.unwrap();
+        assert!(report.dry_run);
+        assert_eq!((report.deleted, report.anonymized), (1, 1));
+        assert!(report.archives.is_empty());
+        assert!(report.partitions_created.is_empty());
+        assert!(repository.find(expired).is_some());
+        assert!(!repository.find(tenant_aggregate).unwrap().1);
+        assert!(!archive_dir.exists());
+    }
+
+    #[tokio::test]
+    async fn test_runs_in_batches_and_creates_partitions() {
+        let now = DateTime::parse_from_rfc3339("2025-11-20T12:00:00Z")
+            .unwrap()
+            .with_timezone(&Utc);
+        let repository = Arc::new(InMemoryRetention::default());
+        for days in 31..38 {
+            repository.seed("sip:alice@other.example.com", days_ago(now, days));
+        }
+        let kept = repository.seed("sip:alice@other.example.com", days_ago(now, 1));
+
+        let mut config = config();
+        config.batch_size = 3;
+        config.partitioning.enabled = true;
+        config.partitioning.months_ahead = 2;
+        let service = CdrRetentionService::new(config, repository.clone());
+
+        let report = service.run_at(now, false).await.unwrap();
+        assert_eq!(report.deleted, 7);
+        assert_eq!(*repository.batches.lock().unwrap(), 3);
+        assert_eq!(repository.records.lock().unwrap().len(), 1);
+        assert!(repository.find(kept).is_some());
+        assert_eq!(
+            report.partitions_created,
+            vec![
+                "call_records_2025_11".to_string(),
+                "call_records_2025_12".to_string(),
+                "call_records_2026_01".to_string(),
+            ]
+        );
+    }
+}
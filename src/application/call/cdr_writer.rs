@@ -0,0 +1,487 @@
+//! CDR updates driven by call events
+//!
+//! The aggregate id of a call is its CDR id, so answers and hangups update
+//! exactly that record even when redirect legs share the SIP Call-ID.
+//! Holds are counted when they start and their time is added when the call
+//! is resumed or ends. The first provisional response ends setup and starts
+//! ring time, and talk time is the answered time less hold time. Early media
+//! and the ACK of the answer are timestamped for billing, and calls hung up
+//! within their trunk's answer validation window are flagged as suspect.
+
+use crate::application::events::{EventBus, EventEnvelope};
+use crate::domain::billing::AnswerSupervisionConfig;
+use crate::domain::call::{CallEvent, EndReason};
+use crate::domain::cdr::{CallStatus, CdrRepository, END_REASON_VOICEMAIL};
+use chrono::{DateTime, Utc};
+use std::collections::HashMap;
+use std::sync::Arc;
+use tokio::sync::broadcast;
+use tokio::task::JoinHandle;
+use tracing::{debug, error, warn};
+use uuid::Uuid;
+
+/// CDR status, end reason and SIP response code for an end reason
+fn end_status(reason: &EndReason) -> (CallStatus, String, Option<u16>) {
+    match reason {
+        EndReason::NormalClearing | EndReason::CallerHangup | EndReason::CalleeHangup => (
+            CallStatus::Completed,
+            "Normal clearing".to_string(),
+            Some(200),
+        ),
+        EndReason::Busy => (CallStatus::Busy, "Busy".to_string(), None),
+        EndReason::Rejected => (CallStatus::Rejected, "Rejected".to_string(), None),
+        EndReason::NoAnswer => (CallStatus::NoAnswer, "No answer".to_string(), None),
+        EndReason::Canceled => (
+            CallStatus::Cancelled,
+            "Call cancelled".to_string(),
+            Some(487),
+        ),
+        EndReason::Failed(reason) => (CallStatus::Failed, reason.clone(), None),
+        EndReason::Maintenance => (
+            CallStatus::Completed,
+            "Ended for maintenance".to_string(),
+            None,
+        ),
+        EndReason::Voicemail => (
+            CallStatus::NoAnswer,
+            END_REASON_VOICEMAIL.to_string(),
+            None,
+        ),
+    }
+}
+
+/// Apply one event to the call's CDR
+///
+/// `holds` has the start of the running hold segment of each held call.
+async fn write_cdr(
+    repository: &dyn CdrRepository,
+    supervision: &AnswerSupervisionConfig,
+    event: &EventEnvelope,
+    holds: &mut HashMap<Uuid, DateTime<Utc>>,
+) -> Result<(), String> {
+    let hold_started = match &event.event {
+        CallEvent::Held(_) => {
+            holds.insert(event.aggregate_id, event.occurred_at);
+            None
+        }
+        CallEvent::Resumed(_) | CallEvent::Ended(_) => holds.remove(&event.aggregate_id),
+        _ => None,
+    };
+    if matches!(event.event, CallEvent::Initiated(_))
+        || (matches!(event.event, CallEvent::Resumed(_)) && hold_started.is_none())
+    {
+        return Ok(());
+    }
+    let mut cdr = match repository.get_by_id(event.aggregate_id).await? {
+        Some(cdr) => cdr,
+        None => {
+            debug!("No CDR {} for call {}", event.aggregate_id, event.call_id);
+            return Ok(());
+        }
+    };
+    if let Some(started) = hold_started {
+        cdr.add_hold_time((event.occurred_at - started).num_seconds() as i32);
+    }
+    match &event.event {
+        CallEvent::Ringing(_) => cdr.mark_ringing(event.occurred_at),
+        CallEvent::EarlyMedia(early) => {
+            cdr.mark_ringing(early.started_at);
+            cdr.mark_early_media(early.started_at);
+        }
+        CallEvent::Answered(answered) => cdr.mark_answered_at(answered.answered_at),
+        CallEvent::Acknowledged(ack) => cdr.mark_ack_complete(ack.acknowledged_at),
+        CallEvent::Held(_) => cdr.mark_held(),
+        CallEvent::Ended(ended) => {
+            let (status, reason, response_code) = end_status(&ended.reason);
+            if matches!(cdr.status, CallStatus::Overflowed | CallStatus::BlockedCos) {
+                // Keeps its disposition for queue and class of service reporting
+                let (status, reason) = (cdr.status, cdr.end_reason.clone());
+                cdr.mark_ended_at(status, reason, response_code, ended.ended_at);
+            } else if cdr.status == CallStatus::Announcement {
+                cdr.mark_ended_at(
+                    CallStatus::Announcement,
+                    Some(reason),
+                    response_code,
+                    ended.ended_at,
+                );
+            } else {
+                cdr.mark_ended_at(status, Some(reason), response_code, ended.ended_at);
+            }
+            if supervision.is_suspect(&cdr) {
+                warn!(
+                    "Call {} hung up {}s after the answer; flagged as suspect",
+                    event.call_id,
+                    cdr.call_duration.unwrap_or(0)
+                );
+                cdr.flag_suspect_answer();
+            }
+        }
+        _ => {}
+    }
+    repository.update(&cdr).await
+}
+
+/// Keep CDRs up to date with answered and ended calls
+pub fn spawn_cdr_writer(bus: &dyn EventBus, repository: Arc<dyn CdrRepository>) -> JoinHandle<()> {
+    spawn_supervised_cdr_writer(bus, repository, AnswerSupervisionConfig::default())
+}
+
+/// Like [`spawn_cdr_writer`], flagging answers per `supervision`
+pub fn spawn_supervised_cdr_writer(
+    bus: &dyn EventBus,
+    repository: Arc<dyn CdrRepository>,
+    supervision: AnswerSupervisionConfig,
+) -> JoinHandle<()> {
+    let mut rx = bus.subscribe();
+    tokio::spawn(async move {
+        let mut holds = HashMap::new();
+        loop {
+            match rx.recv().await {
+                Ok(event) => {
+                    let written =
+                        write_cdr(repository.as_ref(), &supervision, &event, &mut holds).await;
+                    if let Err(e) = written {
+                        error!(
+                            "Failed to update CDR on {} for call {}: {}",
+                            event.event_type(),
+                            event.call_id,
+                            e
+                        );
+                    }
+                }
+                Err(broadcast::error::RecvError::Lagged(skipped)) => {
+                    warn!("Dropped {} call events (CDR writer lagging)", skipped);
+                }
+                Err(broadcast::error::RecvError::Closed) => break,
+            }
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::application::call::CallApplicationService;
+    use crate::domain::billing::{
+        AnswerSupervision, BillingAccount, BillingCycle, BillingManager, Currency, Rate,
+        RatePlan, SupervisionPoint, UsageType,
+    };
+    use crate::domain::call::event::{
+        CallAnswered, CallEnded, CallEventBase, CallHeld, CallResumed, CallRinging,
+    };
+    use crate::domain::call::CallDirection as DomainDirection;
+    use crate::domain::cdr::{CallDetailRecord, CallDirection, MockCdrRepository};
+    use crate::infrastructure::messaging::InProcessEventBus;
+    use crate::infrastructure::persistence::MemoryCdrRepository;
+    use crate::domain::shared::events::EventMetadata;
+    use crate::domain::shared::value_objects::{CallId, SessionId};
+    use std::sync::Mutex;
+
+    fn envelope(cdr_id: Uuid, event: CallEvent, at: DateTime<Utc>) -> EventEnvelope {
+        let mut envelope = EventEnvelope::new("call-1".to_string(), None, 1, event);
+        envelope.aggregate_id = cdr_id;
+        envelope.occurred_at = at;
+        envelope
+    }
+
+    fn base(cdr_id: Uuid, event_type: &str) -> CallEventBase {
+        CallEventBase {
+            metadata: EventMetadata::new(event_type.to_string()),
+            call_id: CallId::from_uuid(cdr_id),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_hold_segments_are_counted_and_timed() {
+        let cdr = CallDetailRecord::new(
+            "call-1".to_string(),
+            "alice".to_string(),
+            "sip:alice@example.com".to_string(),
+            "192.168.1.100".to_string(),
+            "bob".to_string(),
+            "sip:bob@example.com".to_string(),
+            CallDirection::Internal,
+        );
+        let cdr_id = cdr.id;
+        let stored = Arc::new(Mutex::new(cdr));
+        let mut repository = MockCdrRepository::new();
+        let current = stored.clone();
+        repository
+            .expect_get_by_id()
+            .returning(move |_| Ok(Some(current.lock().unwrap().clone())));
+        let updated = stored.clone();
+        repository.expect_update().returning(move |cdr| {
+            *updated.lock().unwrap() = cdr.clone();
+            Ok(())
+        });
+
+        let start = Utc::now();
+        let mut holds = HashMap::new();
+        let events = [
+            (CallEvent::Held(CallHeld { base: base(cdr_id, "call.held") }), 0),
+            (CallEvent::Resumed(CallResumed { base: base(cdr_id, "call.resumed") }), 45),
+            (CallEvent::Held(CallHeld { base: base(cdr_id, "call.held") }), 100),
+            (CallEvent::Resumed(CallResumed { base: base(cdr_id, "call.resumed") }), 130),
+        ];
+        for (event, offset) in events {
+            let at = start + chrono::Duration::seconds(offset);
+            let supervision = AnswerSupervisionConfig::default();
+            write_cdr(&repository, &supervision, &envelope(cdr_id, event, at), &mut holds)
+                .await
+                .unwrap();
+        }
+
+        let cdr = stored.lock().unwrap().clone();
+        assert_eq!(cdr.hold_count, 2);
+        assert_eq!(cdr.hold_duration, 75);
+        assert!(holds.is_empty());
+    }
+
+    /// Builds a call event of the CDR's call at a time
+    type ScriptedEvent = fn(Uuid, DateTime<Utc>) -> CallEvent;
+
+    /// CDR after applying `events`, each at its offset in seconds from the
+    /// INVITE
+    async fn scripted_cdr(events: Vec<(ScriptedEvent, i64)>) -> CallDetailRecord {
+        let start = Utc::now();
+        let mut cdr = CallDetailRecord::new(
+            "call-1".to_string(),
+            "alice".to_string(),
+            "sip:alice@example.com".to_string(),
+            "192.168.1.100".to_string(),
+            "bob".to_string(),
+            "sip:bob@example.com".to_string(),
+            CallDirection::Internal,
+        );
+        cdr.start_time = start;
+        let cdr_id = cdr.id;
+        let repository = MemoryCdrRepository::new();
+        repository.create(&cdr).await.unwrap();
+
+        let supervision = AnswerSupervisionConfig::default();
+        let mut holds = HashMap::new();
+        for (event, offset) in events {
+            let at = start + chrono::Duration::seconds(offset);
+            let event = envelope(cdr_id, event(cdr_id, at), at);
+            write_cdr(&repository, &supervision, &event, &mut holds)
+                .await
+                .unwrap();
+        }
+        repository.get_by_id(cdr_id).await.unwrap().unwrap()
+    }
+
+    fn ringing(cdr_id: Uuid, _: DateTime<Utc>) -> CallEvent {
+        CallEvent::Ringing(CallRinging {
+            base: base(cdr_id, "call.ringing"),
+            session_id: SessionId::new(),
+        })
+    }
+
+    fn answered(cdr_id: Uuid, at: DateTime<Utc>) -> CallEvent {
+        CallEvent::Answered(CallAnswered {
+            base: base(cdr_id, "call.answered"),
+            answered_at: at,
+        })
+    }
+
+    fn held(cdr_id: Uuid, _: DateTime<Utc>) -> CallEvent {
+        CallEvent::Held(CallHeld { base: base(cdr_id, "call.held") })
+    }
+
+    fn resumed(cdr_id: Uuid, _: DateTime<Utc>) -> CallEvent {
+        CallEvent::Resumed(CallResumed { base: base(cdr_id, "call.resumed") })
+    }
+
+    fn hung_up(cdr_id: Uuid, at: DateTime<Utc>) -> CallEvent {
+        CallEvent::Ended(CallEnded {
+            base: base(cdr_id, "call.ended"),
+            reason: EndReason::CallerHangup,
+            ended_at: at,
+            duration_seconds: None,
+        })
+    }
+
+    fn canceled(cdr_id: Uuid, at: DateTime<Utc>) -> CallEvent {
+        CallEvent::Ended(CallEnded {
+            base: base(cdr_id, "call.ended"),
+            reason: EndReason::Canceled,
+            ended_at: at,
+            duration_seconds: None,
+        })
+    }
+
+    #[tokio::test]
+    async fn test_duration_breakdown_with_two_holds() {
+        let cdr = scripted_cdr(vec![
+            (ringing, 2),
+            (answered, 12),
+            (held, 40),
+            (resumed, 70),
+            (held, 100),
+            (resumed, 115),
+            (hung_up, 200),
+        ])
+        .await;
+
+        assert_eq!(cdr.setup_duration, Some(2));
+        assert_eq!(cdr.ring_duration, Some(10));
+        assert_eq!(cdr.call_duration, Some(188));
+        assert_eq!((cdr.hold_count, cdr.hold_duration), (2, 45));
+        assert_eq!(cdr.talk_duration, Some(143));
+        assert_eq!(cdr.total_duration, Some(200));
+    }
+
+    #[tokio::test]
+    async fn test_hangup_while_held_ends_the_hold() {
+        let cdr = scripted_cdr(vec![(answered, 1), (held, 31), (hung_up, 61)]).await;
+
+        assert_eq!(cdr.setup_duration, Some(1));
+        assert_eq!(cdr.ring_duration, None);
+        assert_eq!((cdr.hold_count, cdr.hold_duration), (1, 30));
+        assert_eq!(cdr.talk_duration, Some(30));
+    }
+
+    #[tokio::test]
+    async fn test_cancelled_call_has_ring_time_and_no_talk_time() {
+        let cdr = scripted_cdr(vec![(ringing, 3), (canceled, 21)]).await;
+
+        assert_eq!(cdr.status, CallStatus::Cancelled);
+        assert_eq!(cdr.setup_duration, Some(3));
+        assert_eq!(cdr.ring_duration, Some(18));
+        assert_eq!(cdr.talk_duration, Some(0));
+        assert_eq!(cdr.call_duration, None);
+        assert_eq!(cdr.total_duration, Some(21));
+    }
+
+    /// CDR of a call from alice to a number through `carrier-a`, with the
+    /// service publishing its events and the writer applying them
+    async fn supervised_call(
+        supervision: AnswerSupervisionConfig,
+    ) -> (CallApplicationService, Arc<MemoryCdrRepository>, Uuid, JoinHandle<()>) {
+        let bus = Arc::new(InProcessEventBus::default());
+        let repository = Arc::new(MemoryCdrRepository::new());
+        let writer = spawn_supervised_cdr_writer(bus.as_ref(), repository.clone(), supervision);
+        let mut cdr = CallDetailRecord::new(
+            "call-1".to_string(),
+            "alice".to_string(),
+            "sip:alice@example.com".to_string(),
+            "192.168.1.100".to_string(),
+            "+14155551234".to_string(),
+            "sip:+14155551234@carrier-a.example.net".to_string(),
+            CallDirection::Outbound,
+        );
+        cdr.set_trunk("carrier-a".to_string());
+        repository.create(&cdr).await.unwrap();
+        let service = CallApplicationService::new(bus);
+        service
+            .start("call-1", cdr.id, &cdr.caller_uri, &cdr.callee_uri, DomainDirection::Outbound)
+            .await
+            .unwrap();
+        (service, repository, cdr.id, writer)
+    }
+
+    /// The CDR once the writer has seen the call end
+    async fn ended_cdr(repository: &MemoryCdrRepository, cdr_id: Uuid) -> CallDetailRecord {
+        for _ in 0..100 {
+            if let Some(cdr) = repository.get_by_id(cdr_id).await.unwrap() {
+                if cdr.end_time.is_some() {
+                    return cdr;
+                }
+            }
+            tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+        }
+        panic!("CDR {} never ended", cdr_id);
+    }
+
+    fn billing() -> (BillingManager, Uuid) {
+        let manager = BillingManager::new();
+        let plan = RatePlan::new("Test Plan".to_string(), Currency::USD, BillingCycle::Monthly)
+            .add_rate(Rate::new(UsageType::OutboundMinutes, 0.10));
+        let plan_id = manager.create_rate_plan(plan);
+        let account_id = manager.create_account(BillingAccount::new(
+            Uuid::new_v4(),
+            plan_id,
+            Currency::USD,
+            "billing@example.com".to_string(),
+        ));
+        (manager, account_id)
+    }
+
+    #[tokio::test]
+    async fn test_early_media_then_busy_is_not_billed() {
+        let (service, repository, cdr_id, writer) =
+            supervised_call(AnswerSupervisionConfig::default()).await;
+        service.ring("call-1").await.unwrap();
+        service.early_media("call-1").await.unwrap();
+        service.end("call-1", EndReason::Busy).await.unwrap();
+
+        let cdr = ended_cdr(&repository, cdr_id).await;
+        writer.abort();
+        assert!(cdr.early_media_time.is_some());
+        assert!(cdr.answer_time.is_none() && cdr.ack_time.is_none());
+        assert_eq!(cdr.status, CallStatus::Busy);
+
+        let (manager, account_id) = billing();
+        assert_eq!(manager.record_call(account_id, &cdr).unwrap(), None);
+        assert_eq!(manager.get_account_balance(&account_id), Some(0.0));
+    }
+
+    #[tokio::test]
+    async fn test_instant_answer_and_hangup_is_suspect() {
+        let mut supervision = AnswerSupervisionConfig::default();
+        supervision.trunks.insert(
+            "carrier-a".to_string(),
+            AnswerSupervision {
+                bill_from: SupervisionPoint::AckComplete,
+                validation_window_secs: Some(3),
+            },
+        );
+        let (service, repository, cdr_id, writer) = supervised_call(supervision).await;
+        service.ring("call-1").await.unwrap();
+        service.answer("call-1").await.unwrap();
+        service.acknowledge("call-1").await.unwrap();
+        service.end("call-1", EndReason::CalleeHangup).await.unwrap();
+
+        let cdr = ended_cdr(&repository, cdr_id).await;
+        writer.abort();
+        assert!(cdr.suspect_answer);
+        assert!(cdr.answer_time.is_some() && cdr.ack_time.is_some());
+        assert!(cdr.ack_time >= cdr.answer_time);
+    }
+
+    #[tokio::test]
+    async fn test_call_outside_validation_window_is_not_suspect() {
+        let (service, repository, cdr_id, writer) =
+            supervised_call(AnswerSupervisionConfig::default()).await;
+        service.answer("call-1").await.unwrap();
+        service.end("call-1", EndReason::CalleeHangup).await.unwrap();
+
+        let cdr = ended_cdr(&repository, cdr_id).await;
+        writer.abort();
+        assert!(!cdr.suspect_answer);
+    }
+
+    #[test]
+    fn test_end_status() {
+        assert_eq!(
+            end_status(&EndReason::CalleeHangup).0,
+            CallStatus::Completed
+        );
+        assert_eq!(
+            end_status(&EndReason::Canceled),
+            (
+                CallStatus::Cancelled,
+                "Call cancelled".to_string(),
+                Some(487)
+            )
+        );
+        assert_eq!(
+            end_status(&EndReason::Failed("Not Found".to_string())).1,
+            "Not Found"
+        );
+        assert_eq!(
+            end_status(&EndReason::Voicemail),
+            (CallStatus::NoAnswer, END_REASON_VOICEMAIL.to_string(), None)
+        );
+    }
+}
@@ -1,4 +1,9 @@
 //! Call application services
 
-// Placeholder for call application service
-// TODO: Implement call use cases
+pub mod cdr_retention;
+pub mod cdr_writer;
+pub mod service;
+
+pub use cdr_retention::{spawn_cdr_retention, CdrRetentionService};
+pub use cdr_writer::{spawn_cdr_writer, spawn_supervised_cdr_writer};
+pub use service::CallApplicationService;
@@ -0,0 +1,441 @@
+//! Call lifecycle service
+//!
+//! Mirrors the calls handled by the SIP layer into `Call` aggregates, saves
+//! them after every change and publishes the events they record on the
+//! event bus. Calls are keyed by SIP Call-ID; the aggregate id is chosen by
+//! the caller (the call's CDR id) so subscribers can find records belonging
+//! to the call.
+//!
+//! Saves are optimistic: when the stored call changed since it was loaded
+//! here (another node handled part of the call), it is reloaded and the
+//! change applied again.
+
+use crate::application::events::{EventBus, EventEnvelope};
+use crate::domain::call::{Call, CallDirection, CallRepository, CallState, EndReason, Participant};
+use crate::domain::shared::error::DomainError;
+use crate::domain::shared::result::Result as DomainResult;
+use crate::domain::shared::value_objects::{CallId, EndpointId, SessionId, SipUri};
+use crate::infrastructure::persistence::MemoryCallRepository;
+use std::collections::HashMap;
+use std::sync::Arc;
+use tokio::sync::Mutex;
+use tracing::{debug, error, warn};
+use uuid::Uuid;
+
+/// Times a change is applied before a conflicting save is given up on
+const SAVE_ATTEMPTS: usize = 3;
+
+/// Applies call state changes, saves and publishes the results
+pub struct CallApplicationService {
+    bus: Arc<dyn EventBus>,
+    tenant_id: Option<Uuid>,
+    repository: Arc<dyn CallRepository>,
+    /// Calls as last saved here; held while a change is applied, saved and
+    /// published, which keeps each call's events in order on the bus
+    calls: Mutex<HashMap<String, Call>>,
+}
+
+impl CallApplicationService {
+    pub fn new(bus: Arc<dyn EventBus>) -> Self {
+        Self {
+            bus,
+            tenant_id: None,
+            repository: Arc::new(MemoryCallRepository::new()),
+            calls: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Stamp published events with `tenant_id`
+    pub fn with_tenant(mut self, tenant_id: Uuid) -> Self {
+        self.tenant_id = Some(tenant_id);
+        self
+    }
+
+    /// Save calls to `repository` rather than in memory
+    pub fn with_repository(mut self, repository: Arc<dyn CallRepository>) -> Self {
+        self.repository = repository;
+        self
+    }
+
+    pub fn event_bus(&self) -> Arc<dyn EventBus> {
+        self.bus.clone()
+    }
+
+    /// Start tracking a new call
+    pub async fn start(
+        &self,
+        call_id: &str,
+        aggregate_id: Uuid,
+        caller_uri: &str,
+        callee_uri: &str,
+        direction: CallDirection,
+    ) -> Result<(), String> {
+        let mut calls = self.calls.lock().await;
+        if calls.contains_key(call_id) {
+            return Err(format!("Call {} already exists", call_id));
+        }
+        let mut call = Call::new(
+            CallId::from_uuid(aggregate_id),
+            Self::participant(caller_uri),
+            Self::participant(callee_uri),
+            direction,
+        );
+        self.repository.save(&mut call).await.map_err(|e| match e {
+            DomainError::Conflict(_) => format!("Call {} already exists", call_id),
+            e => format!("Failed to save call {}: {}", call_id, e),
+        })?;
+        let published = self.publish(call_id, &mut call).await;
+        calls.insert(call_id.to_string(), call);
+        published
+    }
+
+    /// Track a call again from its saved aggregate, after a restart or on
+    /// the node taking over the call
+    pub async fn recover(&self, call_id: &str, aggregate_id: Uuid) -> Result<(), String> {
+        let mut calls = self.calls.lock().await;
+        if calls.contains_key(call_id) {
+            return Ok(());
+        }
+        let call = self.load(call_id, &CallId::from_uuid(aggregate_id)).await?;
+        if !call.is_active() {
+            return Err(format!("Call {} already ended", call_id));
+        }
+        debug!("Recovered call {} at version {}", call_id, call.version());
+        calls.insert(call_id.to_string(), call);
+        Ok(())
+    }
+
+    /// The callee is being alerted
+    pub async fn ring(&self, call_id: &str) -> Result<(), String> {
+        self.apply(call_id, |call| call.ring(SessionId::new()))
+            .await
+    }
+
+    /// The callee answered; calls answered without ringing first ring
+    /// implicitly
+    pub async fn answer(&self, call_id: &str) -> Result<(), String> {
+        self.apply(call_id, |call| {
+            if *call.state() == CallState::Initiating {
+                call.ring(SessionId::new())?;
+            }
+            call.answer()
+        })
+        .await
+    }
+
+    /// The callee sent early media (the first time only)
+    pub async fn early_media(&self, call_id: &str) -> Result<(), String> {
+        self.apply(call_id, |call| call.early_media()).await
+    }
+
+    /// The caller's ACK completed the answer
+    pub async fn acknowledge(&self, call_id: &str) -> Result<(), String> {
+        self.apply(call_id, |call| call.acknowledge()).await
+    }
+
+    pub async fn hold(&self, call_id: &str) -> Result<(), String> {
+        self.apply(call_id, |call| call.hold()).await
+    }
+
+    pub async fn resume(&self, call_id: &str) -> Result<(), String> {
+        self.apply(call_id, |call| call.resume()).await
+    }
+
+    /// End the call and stop tracking it
+    pub async fn end(&self, call_id: &str, reason: EndReason) -> Result<(), String> {
+        self.apply(call_id, move |call| call.end(reason.clone())).await
+    }
+
+    /// Number of calls being tracked
+    pub async fn active_call_count(&self) -> usize {
+        self.calls.lock().await.len()
+    }
+
+    /// Apply `change` to a call's aggregate, save it and publish what it
+    /// recorded; on a conflicting save the change is applied again to the
+    /// reloaded call
+    async fn apply<F>(&self, call_id: &str, change: F) -> Result<(), String>
+    where
+        F: Fn(&mut Call) -> DomainResult<()>,
+    {
+        let mut calls = self.calls.lock().await;
+        let mut current = calls
+            .get(call_id)
+            .cloned()
+            .ok_or_else(|| format!("Call {} not found", call_id))?;
+        let mut attempt = 1;
+        let (result, mut call) = loop {
+            let mut call = current.clone();
+            let recorded = call.recorded_events();
+            let result = change(&mut call).map_err(|e| e.to_string());
+            // Events recorded before a failed step are still saved and
+            // published
+            if call.recorded_events() == recorded {
+                break (result, call);
+            }
+            match self.repository.save(&mut call).await {
+                Ok(()) => break (result, call),
+                Err(DomainError::Conflict(reason)) if attempt < SAVE_ATTEMPTS => {
+                    warn!("Call {} changed elsewhere ({}), reloading", call_id, reason);
+                    current = self.load(call_id, call.id()).await?;
+                    attempt += 1;
+                }
+                Err(e) => {
+                    error!("Failed to save call {}: {}", call_id, e);
+                    return Err(format!("Failed to save call {}: {}", call_id, e));
+                }
+            }
+        };
+        let published = self.publish(call_id, &mut call).await;
+        if call.is_active() {
+            calls.insert(call_id.to_string(), call);
+        } else {
+            calls.remove(call_id);
+        }
+        result.and(published)
+    }
+
+    /// A call's saved aggregate
+    async fn load(&self, call_id: &str, id: &CallId) -> Result<Call, String> {
+        self.repository
+            .find_by_id(id)
+            .await
+            .map_err(|e| format!("Failed to load call {}: {}", call_id, e))?
+            .ok_or_else(|| format!("Call {} not found", call_id))
+    }
+
+    /// Drain a call's pending events onto the bus
+    async fn publish(&self, call_id: &str, call: &mut Call) -> Result<(), String> {
+        let pending = call.take_events();
+        // Pending events are the last ones recorded
+        let first = call.recorded_events() - pending.len() as u64;
+        let events: Vec<EventEnvelope> = pending
+            .into_iter()
+            .enumerate()
+            .map(|(i, event)| {
+                EventEnvelope::new(call_id.to_string(), self.tenant_id, first + i as u64 + 1, event)
+            })
+            .collect();
+        if events.is_empty() {
+            return Ok(());
+        }
+        debug!("Publishing {} events for call {}", events.len(), call_id);
+        self.bus.publish(events).await.map_err(|e| {
+            error!("Failed to publish events for call {}: {}", call_id, e);
+            e
+        })
+    }
+
+    /// Participant for a SIP URI; anything the domain cannot parse is kept
+    /// whole as the user
+    fn participant(uri: &str) -> Participant {
+        let sip_uri = SipUri::parse(uri).unwrap_or_else(|_| SipUri::new(uri.to_string(), String::new(), None));
+        Participant::new(EndpointId::new(), sip_uri, None)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::domain::call::CallEvent;
+    use crate::infrastructure::messaging::InProcessEventBus;
+    use tokio::sync::broadcast::error::TryRecvError;
+
+    fn drain(rx: &mut tokio::sync::broadcast::Receiver<EventEnvelope>) -> Vec<EventEnvelope> {
+        let mut events = Vec::new();
+        loop {
+            match rx.try_recv() {
+                Ok(event) => events.push(event),
+                Err(TryRecvError::Empty) => return events,
+                Err(e) => panic!("unexpected receive error: {:?}", e),
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_answer_publishes_one_answered_event_to_each_subscriber() {
+        let bus = Arc::new(InProcessEventBus::default());
+        let tenant_id = Uuid::new_v4();
+        let service = CallApplicationService::new(bus.clone()).with_tenant(tenant_id);
+        let mut cdr_writer = bus.subscribe();
+        let mut broadcaster = bus.subscribe();
+
+        let aggregate_id = Uuid::new_v4();
+        service
+            .start(
+                "call-1",
+                aggregate_id,
+                "sip:alice@example.com",
+                "sip:bob@example.com",
+                CallDirection::Internal,
+            )
+            .await
+            .unwrap();
+        service.ring("call-1").await.unwrap();
+        service.answer("call-1").await.unwrap();
+
+        for rx in [&mut cdr_writer, &mut broadcaster] {
+            let events = drain(rx);
+            let answered: Vec<_> = events
+                .iter()
+                .filter(|e| matches!(e.event, CallEvent::Answered(_)))
+                .collect();
+            assert_eq!(answered.len(), 1);
+            assert_eq!(answered[0].aggregate_id, aggregate_id);
+            assert_eq!(answered[0].call_id, "call-1");
+            assert_eq!(answered[0].tenant_id, Some(tenant_id));
+            assert_eq!(answered[0].event_type(), "call.answered");
+            // Initiated, Ringing, Answered in aggregate order
+            assert_eq!(
+                events.iter().map(|e| e.sequence).collect::<Vec<_>>(),
+                vec![1, 2, 3]
+            );
+        }
+        assert!(drain(&mut cdr_writer).is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_answer_without_ringing_and_end() {
+        let bus = Arc::new(InProcessEventBus::default());
+        let service = CallApplicationService::new(bus.clone());
+        let mut rx = bus.subscribe();
+
+        service
+            .start(
+                "call-2",
+                Uuid::new_v4(),
+                "sip:alice@example.com",
+                "sips:bob@example.com",
+                CallDirection::Internal,
+            )
+            .await
+            .unwrap();
+        service.answer("call-2").await.unwrap();
+        assert!(service.resume("call-2").await.is_err());
+        service
+            .end("call-2", EndReason::NormalClearing)
+            .await
+            .unwrap();
+
+        let types: Vec<_> = drain(&mut rx).iter().map(|e| e.event_type()).collect();
+        assert_eq!(
+            types,
+            vec![
+                "call.initiated",
+                "call.ringing",
+                "call.answered",
+                "call.ended"
+            ]
+        );
+        // Ended calls are no longer tracked
+        assert_eq!(service.active_call_count().await, 0);
+        assert!(service.answer("call-2").await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_conflicting_change_is_applied_to_reloaded_call() {
+        let bus = Arc::new(InProcessEventBus::default());
+        let repository = Arc::new(MemoryCallRepository::new());
+        let node_a = CallApplicationService::new(bus.clone()).with_repository(repository.clone());
+        let node_b = CallApplicationService::new(bus.clone()).with_repository(repository.clone());
+        let mut rx = bus.subscribe();
+
+        let aggregate_id = Uuid::new_v4();
+        node_a
+            .start(
+                "call-3",
+                aggregate_id,
+                "sip:alice@example.com",
+                "sip:bob@example.com",
+                CallDirection::Internal,
+            )
+            .await
+            .unwrap();
+        node_a.answer("call-3").await.unwrap();
+        node_b.recover("call-3", aggregate_id).await.unwrap();
+
+        // Node B still holds the answered call when it ends it
+        node_a.hold("call-3").await.unwrap();
+        node_b
+            .end("call-3", EndReason::NormalClearing)
+            .await
+            .unwrap();
+
+        let stored = repository
+            .find_by_id(&CallId::from_uuid(aggregate_id))
+            .await
+            .unwrap()
+            .unwrap();
+        assert!(matches!(stored.state(), CallState::Ended(_)));
+        // Saved at start, answer, hold and end
+        assert_eq!(stored.version(), 4);
+
+        let events = drain(&mut rx);
+        assert_eq!(
+            events
+                .iter()
+                .map(|e| (e.event_type(), e.sequence))
+                .collect::<Vec<_>>(),
+            vec![
+                ("call.initiated", 1),
+                ("call.ringing", 2),
+                ("call.answered", 3),
+                ("call.held", 4),
+                ("call.ended", 5)
+            ]
+        );
+        assert_eq!(node_b.active_call_count().await, 0);
+    }
+
+    #[tokio::test]
+    async fn test_call_recovered_mid_call_after_restart() {
+        let bus = Arc::new(InProcessEventBus::default());
+        let repository = Arc::new(MemoryCallRepository::new());
+        let aggregate_id = Uuid::new_v4();
+        {
+            let service = CallApplicationService::new(bus.clone()).with_repository(repository.clone());
+            service
+                .start(
+                    "call-4",
+                    aggregate_id,
+                    "sip:alice@example.com",
+                    "sip:bob@example.com",
+                    CallDirection::Internal,
+                )
+                .await
+                .unwrap();
+            service.answer("call-4").await.unwrap();
+            service.hold("call-4").await.unwrap();
+        }
+
+        // The restarted process knows nothing of the call until it recovers it
+        let service = CallApplicationService::new(bus.clone()).with_repository(repository.clone());
+        let mut rx = bus.subscribe();
+        assert!(service.resume("call-4").await.is_err());
+        service.recover("call-4", aggregate_id).await.unwrap();
+        assert_eq!(service.active_call_count().await, 1);
+        service.resume("call-4").await.unwrap();
+        service
+            .end("call-4", EndReason::CallerHangup)
+            .await
+            .unwrap();
+
+        // The event stream carries on where it stopped
+        let events = drain(&mut rx);
+        assert_eq!(
+            events
+                .iter()
+                .map(|e| (e.event_type(), e.sequence))
+                .collect::<Vec<_>>(),
+            vec![("call.resumed", 5), ("call.ended", 6)]
+        );
+        assert!(events.iter().all(|e| e.aggregate_id == aggregate_id));
+        let participant = SipUri::parse("sip:bob@example.com;transport=tcp").unwrap();
+        assert!(repository
+            .find_active_by_participant(&participant)
+            .await
+            .unwrap()
+            .is_empty());
+        assert!(service.recover("call-4", aggregate_id).await.is_err());
+    }
+}
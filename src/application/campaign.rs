@@ -0,0 +1,481 @@
+//! Notification campaigns
+//!
+//! The service runs every campaign created through it: due targets are
+//! called no faster than the campaign's calls per second and no more at once
+//! than its concurrency, within its calling window. No call is placed while
+//! the PBX sheds load or the campaign's trunk is full. Every attempt gets a
+//! CDR linked by the campaign's correlation ID.
+
+use crate::domain::campaign::{
+    Campaign, CampaignContent, CampaignError, CampaignReport, CampaignSettings, DialResult,
+};
+use crate::domain::cdr::{CallDetailRecord, CallDirection, CallStatus, CdrRepository};
+use crate::infrastructure::media::CapacityMonitor;
+use crate::infrastructure::protocols::sip::OverloadMonitor;
+use async_trait::async_trait;
+use chrono::Utc;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::task::JoinHandle;
+use tracing::{error, info};
+use uuid::Uuid;
+
+/// One call to a campaign target
+#[derive(Debug, Clone, PartialEq)]
+pub struct DialAttempt {
+    pub campaign_id: Uuid,
+    pub call_id: String,
+    /// Number presented to the target
+    pub caller_id: String,
+    pub number: String,
+    pub trunk: Option<String>,
+    /// Content with the target's variables substituted
+    pub content: CampaignContent,
+    pub confirm_digit: Option<char>,
+    /// How long an answered call is kept up for its content
+    pub listen: Duration,
+}
+
+/// Places campaign calls
+#[async_trait]
+pub trait CampaignDialer: Send + Sync {
+    /// Call the target and run the content if it answers; returns once
+    /// the call is over
+    async fn dial(&self, attempt: &DialAttempt) -> DialResult;
+}
+
+struct CampaignRun {
+    campaign: Campaign,
+    /// When the last call was placed, for pacing
+    last_dial: Option<Instant>,
+}
+
+/// Creates and runs campaigns
+pub struct CampaignService {
+    dialer: Arc<dyn CampaignDialer>,
+    campaigns: Mutex<HashMap<Uuid, CampaignRun>>,
+    cdr_repository: Option<Arc<dyn CdrRepository>>,
+    overload: Option<Arc<OverloadMonitor>>,
+    capacity: Option<Arc<CapacityMonitor>>,
+}
+
+impl CampaignService {
+    pub fn new(dialer: Arc<dyn CampaignDialer>) -> Self {
+        Self {
+            dialer,
+            campaigns: Mutex::new(HashMap::new()),
+            cdr_repository: None,
+            overload: None,
+            capacity: None,
+        }
+    }
+
+    /// Record campaign calls in `repository`
+    pub fn with_cdr_repository(mut self, repository: Arc<dyn CdrRepository>) -> Self {
+        self.cdr_repository = Some(repository);
+        self
+    }
+
+    /// Place no calls while `overload` sheds load
+    pub fn with_overload_monitor(mut self, overload: Arc<OverloadMonitor>) -> Self {
+        self.overload = Some(overload);
+        self
+    }
+
+    /// Place no calls on a trunk `capacity` reports full
+    pub fn with_capacity_monitor(mut self, capacity: Arc<CapacityMonitor>) -> Self {
+        self.capacity = Some(capacity);
+        self
+    }
+
+    /// Create a campaign; it starts calling on the runner's next tick
+    pub fn create(&self, settings: CampaignSettings) -> Result<CampaignReport, CampaignError> {
+        let campaign = Campaign::new(settings)?;
+        info!(
+            "Campaign {} ({}) created with {} targets",
+            campaign.id,
+            campaign.settings.name,
+            campaign.targets.len()
+        );
+        let report = campaign.report();
+        self.campaigns.lock().unwrap().insert(
+            campaign.id,
+            CampaignRun {
+                campaign,
+                last_dial: None,
+            },
+        );
+        Ok(report)
+    }
+
+    pub fn get(&self, id: Uuid) -> Result<CampaignReport, CampaignError> {
+        self.campaigns
+            .lock()
+            .unwrap()
+            .get(&id)
+            .map(|run| run.campaign.report())
+            .ok_or(CampaignError::NotFound(id))
+    }
+
+    /// Every campaign, oldest first
+    pub fn list(&self) -> Vec<CampaignReport> {
+        let mut reports: Vec<CampaignReport> = self
+            .campaigns
+            .lock()
+            .unwrap()
+            .values()
+            .map(|run| run.campaign.report())
+            .collect();
+        reports.sort_by_key(|report| report.created_at);
+        reports
+    }
+
+    pub fn pause(&self, id: Uuid) -> Result<CampaignReport, CampaignError> {
+        self.update(id, |campaign| campaign.pause())
+    }
+
+    pub fn resume(&self, id: Uuid) -> Result<CampaignReport, CampaignError> {
+        self.update(id, |campaign| campaign.resume())
+    }
+
+    /// End a campaign; calls in progress go on
+    pub fn cancel(&self, id: Uuid) -> Result<CampaignReport, CampaignError> {
+        self.update(id, |campaign| campaign.cancel(Utc::now()))
+    }
+
+    fn update(
+        &self,
+        id: Uuid,
+        change: impl FnOnce(&mut Campaign) -> Result<(), CampaignError>,
+    ) -> Result<CampaignReport, CampaignError> {
+        let mut campaigns = self.campaigns.lock().unwrap();
+        let run = campaigns.get_mut(&id).ok_or(CampaignError::NotFound(id))?;
+        change(&mut run.campaign)?;
+        info!("Campaign {} is {}", id, run.campaign.status);
+        Ok(run.campaign.report())
+    }
+
+    /// Start the calls due now, at most one per campaign; returns how many
+    /// were started
+    pub fn dial_due(self: &Arc<Self>) -> usize {
+        if self.overload.as_ref().is_some_and(|overload| overload.is_shedding()) {
+            return 0;
+        }
+
+        let now = Utc::now();
+        let instant = Instant::now();
+        let mut started = Vec::new();
+        {
+            let mut campaigns = self.campaigns.lock().unwrap();
+            for run in campaigns.values_mut() {
+                let campaign = &mut run.campaign;
+                let paced = run
+                    .last_dial
+                    .is_some_and(|last| instant.duration_since(last) < campaign.settings.pacing());
+                if paced || self.trunk_full(campaign.settings.trunk.as_deref()) {
+                    continue;
+                }
+                let Some(index) = campaign.next_due(now) else {
+                    continue;
+                };
+                let call_id = campaign.start_attempt(index);
+                run.last_dial = Some(instant);
+
+                let settings = &campaign.settings;
+                let target = &settings.targets[index];
+                let attempt = DialAttempt {
+                    campaign_id: campaign.id,
+                    call_id,
+                    caller_id: settings.caller_id.clone(),
+                    number: target.number.clone(),
+                    trunk: settings.trunk.clone(),
+                    content: settings.content.for_target(target),
+                    confirm_digit: settings.confirm_digit,
+                    listen: settings.listen_time(),
+                };
+                started.push((index, attempt, campaign.correlation_id()));
+            }
+        }
+
+        let count = started.len();
+        for (index, attempt, correlation_id) in started {
+            let service = self.clone();
+            tokio::spawn(async move {
+                service.run_attempt(index, attempt, correlation_id).await;
+            });
+        }
+        count
+    }
+
+    fn trunk_full(&self, trunk: Option<&str>) -> bool {
+        let (Some(capacity), Some(trunk)) = (&self.capacity, trunk) else {
+            return false;
+        };
+        capacity.snapshot().trunks.iter().any(|usage| {
+            usage.trunk == trunk && usage.limit.is_some_and(|limit| usage.channels >= limit)
+        })
+    }
+
+    async fn run_attempt(&self, index: usize, attempt: DialAttempt, correlation_id: String) {
+        info!(
+            "Campaign {} calling {} ({})",
+            attempt.campaign_id, attempt.number, attempt.call_id
+        );
+        let mut cdr = CallDetailRecord::new(
+            attempt.call_id.clone(),
+            attempt.caller_id.clone(),
+            format!("sip:{}", attempt.caller_id),
+            "0.0.0.0".to_string(),
+            attempt.number.clone(),
+            format!("sip:{}", attempt.number),
+            CallDirection::Outbound,
+        );
+        cdr.set_correlation_id(correlation_id);
+        if let Some(trunk) = &attempt.trunk {
+            cdr.set_trunk(trunk.clone());
+        }
+        self.save_cdr(&cdr, true).await;
+
+        let result = self.dialer.dial(&attempt).await;
+
+        let response_code = Some(result.status).filter(|status| *status != 0);
+        if result.is_answered() {
+            cdr.mark_answered();
+            cdr.mark_ended(CallStatus::Completed, None, response_code);
+        } else {
+            let status = if result.is_busy() {
+                CallStatus::Busy
+            } else if result.is_no_answer() {
+                CallStatus::NoAnswer
+            } else {
+                CallStatus::Failed
+            };
+            cdr.mark_ended(status, result.cause.clone(), response_code);
+        }
+        self.save_cdr(&cdr, false).await;
+
+        let mut campaigns = self.campaigns.lock().unwrap();
+        if let Some(run) = campaigns.get_mut(&attempt.campaign_id) {
+            run.campaign.finish_attempt(index, &result, Utc::now());
+            let target = &run.campaign.targets[index];
+            info!(
+                "Campaign {} call to {}: {}",
+                attempt.campaign_id,
+                attempt.number,
+                target.last_result.as_deref().unwrap_or_default()
+            );
+        }
+    }
+
+    async fn save_cdr(&self, cdr: &CallDetailRecord, create: bool) {
+        if let Some(repository) = &self.cdr_repository {
+            let result = if create {
+                repository.create(cdr).await
+            } else {
+                repository.update(cdr).await
+            };
+            if let Err(e) = result {
+                error!("Failed to save CDR of campaign call {}: {}", cdr.call_id, e);
+            }
+        }
+    }
+}
+
+/// Start due campaign calls each `tick`
+pub fn spawn_campaign_runner(service: Arc<CampaignService>, tick: Duration) -> JoinHandle<()> {
+    tokio::spawn(async move {
+        let mut ticker = tokio::time::interval(tick);
+        loop {
+            ticker.tick().await;
+            service.dial_due();
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::domain::campaign::{CampaignStatus, CampaignTarget, TargetOutcome};
+    use crate::domain::cdr::MockCdrRepository;
+    use crate::infrastructure::media::{CapacityConfig, CodecInfo};
+    use crate::infrastructure::protocols::sip::{OverloadConfig, OverloadSample};
+    use std::collections::VecDeque;
+
+    /// Scripted results per number; answers numbers without a script
+    #[derive(Default)]
+    struct FakeDialer {
+        scripts: Mutex<HashMap<String, VecDeque<DialResult>>>,
+        dialed: Mutex<Vec<(String, Instant)>>,
+    }
+
+    impl FakeDialer {
+        fn script(self, number: &str, results: Vec<DialResult>) -> Self {
+            self.scripts
+                .lock()
+                .unwrap()
+                .insert(number.to_string(), results.into());
+            self
+        }
+    }
+
+    #[async_trait]
+    impl CampaignDialer for FakeDialer {
+        async fn dial(&self, attempt: &DialAttempt) -> DialResult {
+            self.dialed
+                .lock()
+                .unwrap()
+                .push((attempt.number.clone(), Instant::now()));
+            tokio::time::sleep(Duration::from_millis(20)).await;
+            self.scripts
+                .lock()
+                .unwrap()
+                .get_mut(&attempt.number)
+                .and_then(VecDeque::pop_front)
+                .unwrap_or_else(DialResult::answered)
+        }
+    }
+
+    fn settings(numbers: &[&str]) -> CampaignSettings {
+        let mut settings = CampaignSettings::new(
+            "School closure",
+            "5000",
+            numbers.iter().map(|n| CampaignTarget::new(n)).collect(),
+            CampaignContent::Announcement {
+                prompts: vec!["closure".to_string()],
+            },
+        );
+        settings.confirm_digit = Some('1');
+        settings.calls_per_second = 20.0;
+        settings.max_concurrent = 4;
+        settings.retry.retry_delay_secs = 0;
+        settings
+    }
+
+    async fn run_to_end(service: &Arc<CampaignService>, id: Uuid) -> CampaignReport {
+        for _ in 0..400 {
+            service.dial_due();
+            let report = service.get(id).unwrap();
+            if report.status == CampaignStatus::Completed {
+                return report;
+            }
+            tokio::time::sleep(Duration::from_millis(5)).await;
+        }
+        panic!("campaign {} did not complete", id);
+    }
+
+    #[tokio::test]
+    async fn test_campaign_paces_retries_and_tallies() {
+        let dialer = Arc::new(
+            FakeDialer::default()
+                .script("101", vec![DialResult::answered().with_digits("1")])
+                .script("102", vec![DialResult::rejected(486), DialResult::answered()])
+                .script(
+                    "103",
+                    vec![DialResult::answered().with_hangup_after(Duration::from_millis(500))],
+                )
+                .script("104", vec![DialResult::rejected(480); 3])
+                .script("105", vec![DialResult::rejected(404)]),
+        );
+
+        let saved = Arc::new(Mutex::new(Vec::<CallDetailRecord>::new()));
+        let mut cdrs = MockCdrRepository::new();
+        let created = saved.clone();
+        cdrs.expect_create().returning(move |cdr| {
+            created.lock().unwrap().push(cdr.clone());
+            Ok(())
+        });
+        let updated = saved.clone();
+        cdrs.expect_update().returning(move |cdr| {
+            let mut saved = updated.lock().unwrap();
+            saved.retain(|c| c.id != cdr.id);
+            saved.push(cdr.clone());
+            Ok(())
+        });
+
+        let service = Arc::new(CampaignService::new(dialer.clone()).with_cdr_repository(Arc::new(cdrs)));
+        let campaign = service
+            .create(settings(&["101", "102", "103", "104", "105"]))
+            .unwrap();
+        let report = run_to_end(&service, campaign.id).await;
+
+        assert_eq!(report.confirmed, 1);
+        assert_eq!(report.answered, 1);
+        assert_eq!(report.machine_suspected, 1);
+        assert_eq!(report.failed, 2);
+        // 102 once more, 104 until it runs out of attempts
+        assert_eq!(report.attempts, 8);
+        let outcome = |number: &str| {
+            report
+                .targets
+                .iter()
+                .find(|t| t.number == number)
+                .and_then(|t| t.outcome.clone())
+        };
+        assert_eq!(outcome("102"), Some(TargetOutcome::Answered));
+        assert_eq!(
+            outcome("104"),
+            Some(TargetOutcome::Failed {
+                cause: "No answer".to_string()
+            })
+        );
+
+        // No two calls closer than the pacing allows
+        let dialed = dialer.dialed.lock().unwrap();
+        assert_eq!(dialed.len(), 8);
+        for pair in dialed.windows(2) {
+            assert!(pair[1].1.duration_since(pair[0].1) >= Duration::from_millis(45));
+        }
+
+        let saved = saved.lock().unwrap();
+        assert_eq!(saved.len(), 8);
+        let correlation_id = format!("campaign-{}", campaign.id);
+        assert!(saved
+            .iter()
+            .all(|cdr| cdr.correlation_id.as_deref() == Some(correlation_id.as_str())));
+        assert_eq!(
+            saved.iter().filter(|cdr| cdr.status == CallStatus::NoAnswer).count(),
+            3
+        );
+    }
+
+    #[tokio::test]
+    async fn test_no_calls_while_shedding_or_trunk_full() {
+        let overload = Arc::new(OverloadMonitor::new(OverloadConfig::default()));
+        let mut config = CapacityConfig::default();
+        config.trunk_channels.insert("carrier".to_string(), 1);
+        let capacity = Arc::new(CapacityMonitor::new(config));
+        let dialer = Arc::new(FakeDialer::default());
+        let service = Arc::new(
+            CampaignService::new(dialer.clone())
+                .with_overload_monitor(overload.clone())
+                .with_capacity_monitor(capacity.clone()),
+        );
+        let mut settings = settings(&["101"]);
+        settings.trunk = Some("carrier".to_string());
+        let campaign = service.create(settings).unwrap();
+
+        overload.observe(OverloadSample {
+            transactions: 7500,
+            ..Default::default()
+        });
+        assert!(overload.is_shedding());
+        assert_eq!(service.dial_due(), 0);
+
+        overload.observe(OverloadSample::default());
+        let pcma = CodecInfo::new(8, "PCMA".to_string(), 8000);
+        capacity.call_started("other", Some("carrier"), &pcma, None);
+        assert_eq!(service.dial_due(), 0);
+
+        capacity.call_ended("other");
+        service.pause(campaign.id).unwrap();
+        assert_eq!(service.dial_due(), 0);
+        service.resume(campaign.id).unwrap();
+        assert_eq!(service.dial_due(), 1);
+        assert_eq!(run_to_end(&service, campaign.id).await.answered, 1);
+        assert!(matches!(
+            service.cancel(campaign.id),
+            Err(CampaignError::InvalidState(CampaignStatus::Completed))
+        ));
+    }
+}
@@ -0,0 +1,15 @@
+//! Internal chat and presence for agents and supervisors
+//!
+//! Users with an authenticated WebSocket send each other messages and
+//! follow each other's presence through the call control protocol (see
+//! `interface::api::ws_protocol`). Messages are kept in the
+//! `MessageRepository`, so the SIP MESSAGE history and the chat history are
+//! the same.
+
+pub mod service;
+
+pub use service::{
+    spawn_agent_presence, spawn_call_presence, spawn_chat_retention, ChatConfig, ChatMessage,
+    ChatNotice, ChatPolicy, ChatPresence, ChatReceipt, ChatService, ChatSession, MessageForwarder,
+    PresenceUpdate, ReceiptStatus,
+};
@@ -0,0 +1,716 @@
+//! Chat message routing and presence
+//!
+//! A message goes to every open WebSocket session of its recipient and
+//! counts as delivered once one of them took it; the sender's sessions get
+//! a receipt, and another when the recipient reads it. Recipients without
+//! a session get the message as a SIP MESSAGE through the
+//! [`MessageForwarder`]; a message nobody could take stays pending and is
+//! sent when the recipient opens a session or registers.
+//!
+//! A user's presence is, in this order of precedence:
+//! - do-not-disturb, set by the user or by a queue pausing them for DND
+//! - on-call, while they are in an established call of the `CallRouter`
+//! - offline, without an open session
+//! - away, set by the user or while they are paused or logged out of a queue
+//! - available
+//!
+//! Sessions that subscribed to a user get every change.
+
+use crate::application::events::EventBus;
+use crate::domain::call_queue::{AgentAvailability, AgentStateChange, AgentStateReason};
+use crate::domain::call_queue_engine::CallQueueEngine;
+use crate::domain::instant_messaging::{InstantMessage, MessageFilter, MessageRepository, MessageStatus};
+use crate::domain::shared::SortOrder;
+use crate::domain::user::UserRepository;
+use crate::infrastructure::protocols::sip::registration_events::aor_user;
+use crate::infrastructure::protocols::sip::{CallRouter, CallState};
+use async_trait::async_trait;
+use chrono::{DateTime, Duration, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::sync::{Arc, Mutex};
+use tokio::sync::{broadcast, mpsc};
+use tokio::task::JoinHandle;
+use tracing::{debug, error, info, warn};
+use uuid::Uuid;
+
+/// Notices queued per session before further ones are dropped
+const SESSION_QUEUE: usize = 64;
+
+/// Users read per page when applying retention
+const RETENTION_PAGE: i64 = 500;
+
+/// Chat settings of a tenant
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ChatPolicy {
+    /// Whether the tenant's users may chat and follow presence
+    pub enabled: bool,
+    /// Messages older than this many days are deleted; 0 keeps them
+    pub retention_days: u32,
+}
+
+impl Default for ChatPolicy {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            retention_days: 0,
+        }
+    }
+}
+
+/// Internal chat settings
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ChatConfig {
+    /// Policy of tenants without their own
+    pub default_policy: ChatPolicy,
+    /// Per-tenant policies, keyed by SIP realm
+    pub tenants: HashMap<String, ChatPolicy>,
+    /// Seconds between retention runs
+    pub retention_interval_secs: u64,
+}
+
+impl Default for ChatConfig {
+    fn default() -> Self {
+        Self {
+            default_policy: ChatPolicy::default(),
+            tenants: HashMap::new(),
+            retention_interval_secs: 3600,
+        }
+    }
+}
+
+impl ChatConfig {
+    pub fn policy_for(&self, realm: &str) -> &ChatPolicy {
+        self.tenants.get(realm).unwrap_or(&self.default_policy)
+    }
+
+    /// Whether any tenant deletes old messages
+    pub fn has_retention(&self) -> bool {
+        self.tenants
+            .values()
+            .chain(std::iter::once(&self.default_policy))
+            .any(|policy| policy.retention_days > 0)
+    }
+}
+
+/// Presence of a chat user
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ChatPresence {
+    Available,
+    OnCall,
+    Away,
+    DoNotDisturb,
+    Offline,
+}
+
+impl ChatPresence {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Available => "available",
+            Self::OnCall => "on_call",
+            Self::Away => "away",
+            Self::DoNotDisturb => "do_not_disturb",
+            Self::Offline => "offline",
+        }
+    }
+}
+
+/// A chat message as sent to sessions
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ChatMessage {
+    pub id: Uuid,
+    pub from: String,
+    pub to: String,
+    pub text: String,
+    pub sent_at: DateTime<Utc>,
+}
+
+impl From<&InstantMessage> for ChatMessage {
+    fn from(message: &InstantMessage) -> Self {
+        Self {
+            id: message.id,
+            from: message.from.clone(),
+            to: message.to.clone(),
+            text: String::from_utf8_lossy(&message.content).into_owned(),
+            sent_at: message.timestamp,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ReceiptStatus {
+    Delivered,
+    Read,
+}
+
+/// Tells a sender its message reached or was read by the recipient
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ChatReceipt {
+    pub message_id: Uuid,
+    /// Recipient of the message
+    pub by: String,
+    pub status: ReceiptStatus,
+    pub at: DateTime<Utc>,
+}
+
+/// Presence of a user changed (or was asked for)
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PresenceUpdate {
+    pub username: String,
+    pub state: ChatPresence,
+}
+
+/// What a session is sent
+#[derive(Debug, Clone, PartialEq)]
+pub enum ChatNotice {
+    Message(ChatMessage),
+    Receipt(ChatReceipt),
+    Presence(PresenceUpdate),
+}
+
+/// Sends a message toward the recipient's registered devices
+#[async_trait]
+pub trait MessageForwarder: Send + Sync {
+    /// Forward a stored pending message; returns whether it was sent to
+    /// any device (delivery is confirmed later by the device)
+    async fn forward(&self, message: &mut InstantMessage) -> bool;
+}
+
+struct Session {
+    username: String,
+    tx: mpsc::Sender<ChatNotice>,
+    /// Users whose presence the session follows
+    watching: HashSet<String>,
+}
+
+#[derive(Default)]
+struct ChatState {
+    next_session: u64,
+    sessions: HashMap<u64, Session>,
+    /// Set by the users themselves
+    manual: HashMap<String, ChatPresence>,
+    /// Derived from queue agent state
+    agent: HashMap<String, ChatPresence>,
+    on_call: HashSet<String>,
+    /// Last presence sent to watchers; users not in it were offline
+    published: HashMap<String, ChatPresence>,
+}
+
+impl ChatState {
+    fn presence(&self, username: &str) -> ChatPresence {
+        let manual = self.manual.get(username).copied();
+        let agent = self.agent.get(username).copied();
+        let set = |state| manual == Some(state) || agent == Some(state);
+        if set(ChatPresence::DoNotDisturb) {
+            ChatPresence::DoNotDisturb
+        } else if self.on_call.contains(username) {
+            ChatPresence::OnCall
+        } else if !self.sessions.values().any(|s| s.username == username) {
+            ChatPresence::Offline
+        } else if set(ChatPresence::Away) {
+            ChatPresence::Away
+        } else {
+            ChatPresence::Available
+        }
+    }
+
+    /// Send `notice` to every session of `username`; returns whether any
+    /// took it
+    fn push(&self, username: &str, notice: &ChatNotice) -> bool {
+        let mut taken = false;
+        for session in self.sessions.values().filter(|s| s.username == username) {
+            match session.tx.try_send(notice.clone()) {
+                Ok(()) => taken = true,
+                Err(e) => warn!("Dropped chat notice for a session of {}: {}", username, e),
+            }
+        }
+        taken
+    }
+
+    /// Tell watchers about the users of `users` whose presence changed
+    fn publish<'a>(&mut self, users: impl IntoIterator<Item = &'a str>) {
+        for username in users {
+            let state = self.presence(username);
+            let previous = self
+                .published
+                .get(username)
+                .copied()
+                .unwrap_or(ChatPresence::Offline);
+            if state == previous {
+                continue;
+            }
+            debug!("Presence of {} is now {}", username, state.as_str());
+            if state == ChatPresence::Offline {
+                self.published.remove(username);
+            } else {
+                self.published.insert(username.to_string(), state);
+            }
+            let notice = ChatNotice::Presence(PresenceUpdate {
+                username: username.to_string(),
+                state,
+            });
+            for session in self.sessions.values().filter(|s| s.watching.contains(username)) {
+                if let Err(e) = session.tx.try_send(notice.clone()) {
+                    warn!("Dropped presence of {} for {}: {}", username, session.username, e);
+                }
+            }
+        }
+    }
+}
+
+/// Routes chat messages and publishes presence
+pub struct ChatService {
+    config: ChatConfig,
+    repository: Arc<dyn MessageRepository>,
+    forwarder: Option<Arc<dyn MessageForwarder>>,
+    call_router: Option<Arc<CallRouter>>,
+    user_repository: Option<Arc<dyn UserRepository>>,
+    state: Mutex<ChatState>,
+}
+
+impl ChatService {
+    pub fn new(config: ChatConfig, repository: Arc<dyn MessageRepository>) -> Self {
+        Self {
+            config,
+            repository,
+            forwarder: None,
+            call_router: None,
+            user_repository: None,
+            state: Mutex::new(ChatState::default()),
+        }
+    }
+
+    /// Send messages to recipients without a session as SIP MESSAGE
+    pub fn with_forwarder(mut self, forwarder: Arc<dyn MessageForwarder>) -> Self {
+        self.forwarder = Some(forwarder);
+        self
+    }
+
+    /// Derive on-call presence from the router's calls
+    pub fn with_call_router(mut self, router: Arc<CallRouter>) -> Self {
+        self.call_router = Some(router);
+        self
+    }
+
+    /// Users whose messages retention applies to
+    pub fn with_user_repository(mut self, repository: Arc<dyn UserRepository>) -> Self {
+        self.user_repository = Some(repository);
+        self
+    }
+
+    pub fn config(&self) -> &ChatConfig {
+        &self.config
+    }
+
+    /// Whether users of `realm` may chat
+    pub fn enabled_for(&self, realm: &str) -> bool {
+        self.config.policy_for(realm).enabled
+    }
+
+    /// Open a session for `username`; notices for it arrive on the
+    /// receiver until the session is dropped
+    ///
+    /// Messages that waited for the user are sent right away.
+    pub async fn connect(
+        self: &Arc<Self>,
+        username: &str,
+    ) -> (ChatSession, mpsc::Receiver<ChatNotice>) {
+        let (tx, rx) = mpsc::channel(SESSION_QUEUE);
+        let id = {
+            let mut state = self.state.lock().unwrap();
+            state.next_session += 1;
+            let id = state.next_session;
+            state.sessions.insert(
+                id,
+                Session {
+                    username: username.to_string(),
+                    tx,
+                    watching: HashSet::new(),
+                },
+            );
+            state.publish([username]);
+            id
+        };
+        info!("Chat session {} opened for {}", id, username);
+
+        match self.repository.list_pending_for(username).await {
+            Ok(pending) => {
+                for mut message in pending {
+                    let notice = ChatNotice::Message(ChatMessage::from(&message));
+                    let taken = self.state.lock().unwrap().push(username, &notice);
+                    if taken {
+                        self.delivered(&mut message).await;
+                    }
+                }
+            }
+            Err(e) => warn!("Failed to list pending messages for {}: {}", username, e),
+        }
+
+        let session = ChatSession {
+            service: self.clone(),
+            id,
+            username: username.to_string(),
+        };
+        (session, rx)
+    }
+
+    fn disconnect(&self, id: u64) {
+        let mut state = self.state.lock().unwrap();
+        if let Some(session) = state.sessions.remove(&id) {
+            info!("Chat session {} of {} closed", id, session.username);
+            state.publish([session.username.as_str()]);
+        }
+    }
+
+    /// Send `text` from `from` (a user of `realm`) to `to`
+    pub async fn send(
+        &self,
+        from: &str,
+        realm: &str,
+        to: &str,
+        text: String,
+    ) -> Result<ChatMessage, String> {
+        if !self.enabled_for(realm) {
+            return Err(format!("Chat is disabled for {}", realm));
+        }
+        if text.is_empty() {
+            return Err("Message is empty".to_string());
+        }
+
+        let mut message = InstantMessage::text(from.to_string(), to.to_string(), text);
+        self.repository.save(&message).await?;
+        let chat = ChatMessage::from(&message);
+
+        let taken = self
+            .state
+            .lock()
+            .unwrap()
+            .push(to, &ChatNotice::Message(chat.clone()));
+        if taken {
+            self.delivered(&mut message).await;
+        } else if let Some(forwarder) = &self.forwarder {
+            if !forwarder.forward(&mut message).await {
+                debug!("{} is offline, message {} stored", to, message.id);
+            }
+        }
+        Ok(chat)
+    }
+
+    async fn delivered(&self, message: &mut InstantMessage) {
+        message.mark_delivered();
+        message.next_attempt_at = None;
+        if let Err(e) = self.repository.update(message).await {
+            warn!("Failed to mark message {} delivered: {}", message.id, e);
+        }
+        self.receipt(message, ReceiptStatus::Delivered);
+    }
+
+    fn receipt(&self, message: &InstantMessage, status: ReceiptStatus) {
+        let receipt = ChatReceipt {
+            message_id: message.id,
+            by: message.to.clone(),
+            status,
+            at: Utc::now(),
+        };
+        self.state
+            .lock()
+            .unwrap()
+            .push(&message.from, &ChatNotice::Receipt(receipt));
+    }
+
+    /// `username` read a message sent to them
+    pub async fn mark_read(&self, username: &str, message_id: Uuid) -> Result<(), String> {
+        let mut message = self
+            .repository
+            .get_by_id(message_id)
+            .await?
+            .filter(|message| message.to == username)
+            .ok_or_else(|| format!("Message {} not found", message_id))?;
+        if message.status == MessageStatus::Read {
+            return Ok(());
+        }
+        message.mark_read();
+        self.repository.update(&message).await?;
+        self.receipt(&message, ReceiptStatus::Read);
+        Ok(())
+    }
+
+    /// Set the presence `username` chose; only available, away and
+    /// do-not-disturb can be chosen
+    pub fn set_presence(&self, username: &str, presence: ChatPresence) -> Result<(), String> {
+        let mut state = self.state.lock().unwrap();
+        match presence {
+            ChatPresence::Available => {
+                state.manual.remove(username);
+            }
+            ChatPresence::Away | ChatPresence::DoNotDisturb => {
+                state.manual.insert(username.to_string(), presence);
+            }
+            ChatPresence::OnCall | ChatPresence::Offline => {
+                return Err(format!("Presence {} cannot be set", presence.as_str()));
+            }
+        }
+        state.publish([username]);
+        Ok(())
+    }
+
+    /// Current presence of `username`
+    pub fn presence(&self, username: &str) -> ChatPresence {
+        self.state.lock().unwrap().presence(username)
+    }
+
+    /// Follow the presence of `users` in session `id`; their current
+    /// presence is sent right away
+    fn watch(&self, id: u64, users: Vec<String>) {
+        let mut state = self.state.lock().unwrap();
+        let updates: Vec<PresenceUpdate> = users
+            .iter()
+            .map(|username| PresenceUpdate {
+                username: username.clone(),
+                state: state.presence(username),
+            })
+            .collect();
+        if let Some(session) = state.sessions.get_mut(&id) {
+            session.watching.extend(users);
+            for update in updates {
+                if let Err(e) = session.tx.try_send(ChatNotice::Presence(update)) {
+                    warn!("Dropped presence for {}: {}", session.username, e);
+                }
+            }
+        }
+    }
+
+    /// Recompute on-call presence from the router's established calls
+    pub async fn refresh_calls(&self) {
+        let Some(router) = &self.call_router else {
+            return;
+        };
+        let on_call: HashSet<String> = router
+            .get_active_calls()
+            .await
+            .into_iter()
+            .filter(|call| call.state == CallState::Established.name())
+            .flat_map(|call| [call.caller_uri, call.callee_uri])
+            .filter_map(|uri| aor_user(&uri).map(str::to_string))
+            .collect();
+
+        let mut state = self.state.lock().unwrap();
+        let changed: Vec<String> = state
+            .on_call
+            .symmetric_difference(&on_call)
+            .cloned()
+            .collect();
+        state.on_call = on_call;
+        state.publish(changed.iter().map(String::as_str));
+    }
+
+    /// Follow a queue agent's state: paused for DND is do-not-disturb,
+    /// otherwise paused or logged out is away
+    pub fn agent_state_changed(&self, change: &AgentStateChange) {
+        let mut state = self.state.lock().unwrap();
+        match change.to {
+            AgentAvailability::Available => {
+                state.agent.remove(&change.username);
+            }
+            AgentAvailability::Paused if change.reason == AgentStateReason::DndEnabled => {
+                state
+                    .agent
+                    .insert(change.username.clone(), ChatPresence::DoNotDisturb);
+            }
+            AgentAvailability::Paused | AgentAvailability::LoggedOut => {
+                state.agent.insert(change.username.clone(), ChatPresence::Away);
+            }
+        }
+        state.publish([change.username.as_str()]);
+    }
+
+    /// Delete messages older than the retention of their users' tenants;
+    /// returns how many were deleted
+    pub async fn apply_retention(&self, now: DateTime<Utc>) -> Result<u64, String> {
+        let Some(users) = &self.user_repository else {
+            return Ok(0);
+        };
+        let sort = SortOrder::ascending("id");
+        let mut deleted = 0;
+        let mut offset = 0;
+        loop {
+            let page = users
+                .list(&sort, RETENTION_PAGE, offset)
+                .await
+                .map_err(|e| e.to_string())?;
+            for user in &page {
+                let days = self.config.policy_for(&user.realm).retention_days;
+                if days == 0 {
+                    continue;
+                }
+                let filter = MessageFilter {
+                    peer: None,
+                    before: Some(now - Duration::days(days as i64)),
+                };
+                deleted += self.repository.delete_history(&user.username, &filter).await?;
+            }
+            if (page.len() as i64) < RETENTION_PAGE {
+                break;
+            }
+            offset += RETENTION_PAGE;
+        }
+        if deleted > 0 {
+            info!("Deleted {} expired chat messages", deleted);
+        }
+        Ok(deleted)
+    }
+}
+
+/// An open chat session; closed when dropped
+pub struct ChatSession {
+    service: Arc<ChatService>,
+    id: u64,
+    username: String,
+}
+
+impl ChatSession {
+    pub fn username(&self) -> &str {
+        &self.username
+    }
+
+    pub fn service(&self) -> &Arc<ChatService> {
+        &self.service
+    }
+
+    /// Follow the presence of `users`
+    pub fn watch(&self, users: Vec<String>) {
+        self.service.watch(self.id, users);
+    }
+}
+
+impl Drop for ChatSession {
+    fn drop(&mut self) {
+        self.service.disconnect(self.id);
+    }
+}
+
+/// Update on-call presence on every call event
+pub fn spawn_call_presence(service: Arc<ChatService>, bus: &dyn EventBus) -> JoinHandle<()> {
+    let mut rx = bus.subscribe();
+    tokio::spawn(async move {
+        loop {
+            match rx.recv().await {
+                Ok(_) => service.refresh_calls().await,
+                Err(broadcast::error::RecvError::Lagged(skipped)) => {
+                    warn!("Missed {} call events for presence", skipped);
+                    service.refresh_calls().await;
+                }
+                Err(broadcast::error::RecvError::Closed) => break,
+            }
+        }
+    })
+}
+
+/// Update presence on every queue agent state change
+pub fn spawn_agent_presence(service: Arc<ChatService>, engine: &CallQueueEngine) -> JoinHandle<()> {
+    let mut rx = engine.subscribe_agent_states();
+    tokio::spawn(async move {
+        loop {
+            match rx.recv().await {
+                Ok(change) => service.agent_state_changed(&change),
+                Err(broadcast::error::RecvError::Lagged(skipped)) => {
+                    warn!("Missed {} agent state changes for presence", skipped);
+                }
+                Err(broadcast::error::RecvError::Closed) => break,
+            }
+        }
+    })
+}
+
+/// Apply chat retention every `retention_interval_secs`
+pub fn spawn_chat_retention(service: Arc<ChatService>) -> JoinHandle<()> {
+    let interval = std::time::Duration::from_secs(service.config.retention_interval_secs.max(1));
+    tokio::spawn(async move {
+        let mut ticker = tokio::time::interval(interval);
+        loop {
+            ticker.tick().await;
+            if let Err(e) = service.apply_retention(Utc::now()).await {
+                error!("Chat retention failed: {}", e);
+            }
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::infrastructure::persistence::MemoryMessageRepository;
+
+    fn agent_change(username: &str, to: AgentAvailability, reason: AgentStateReason) -> AgentStateChange {
+        AgentStateChange {
+            queue_id: Uuid::new_v4(),
+            member_id: Uuid::new_v4(),
+            agent_id: 1,
+            username: username.to_string(),
+            from: AgentAvailability::Available,
+            to,
+            reason,
+            occurred_at: Utc::now(),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_presence_precedence() {
+        let service = Arc::new(ChatService::new(
+            ChatConfig::default(),
+            Arc::new(MemoryMessageRepository::new()),
+        ));
+        assert_eq!(service.presence("alice"), ChatPresence::Offline);
+
+        let (_session, _rx) = service.connect("alice").await;
+        assert_eq!(service.presence("alice"), ChatPresence::Available);
+
+        service.agent_state_changed(&agent_change(
+            "alice",
+            AgentAvailability::Paused,
+            AgentStateReason::Manual,
+        ));
+        assert_eq!(service.presence("alice"), ChatPresence::Away);
+
+        service.state.lock().unwrap().on_call.insert("alice".to_string());
+        assert_eq!(service.presence("alice"), ChatPresence::OnCall);
+
+        service.set_presence("alice", ChatPresence::DoNotDisturb).unwrap();
+        assert_eq!(service.presence("alice"), ChatPresence::DoNotDisturb);
+        assert!(service.set_presence("alice", ChatPresence::OnCall).is_err());
+
+        service.set_presence("alice", ChatPresence::Available).unwrap();
+        service.agent_state_changed(&agent_change(
+            "alice",
+            AgentAvailability::Paused,
+            AgentStateReason::DndEnabled,
+        ));
+        assert_eq!(service.presence("alice"), ChatPresence::DoNotDisturb);
+    }
+
+    #[tokio::test]
+    async fn test_disabled_tenant_cannot_send() {
+        let mut config = ChatConfig::default();
+        config.tenants.insert(
+            "acme.example.com".to_string(),
+            ChatPolicy {
+                enabled: false,
+                retention_days: 0,
+            },
+        );
+        let service = ChatService::new(config, Arc::new(MemoryMessageRepository::new()));
+
+        assert!(service
+            .send("alice", "acme.example.com", "bob", "hi".to_string())
+            .await
+            .is_err());
+        assert!(service
+            .send("alice", "example.com", "bob", "hi".to_string())
+            .await
+            .is_ok());
+    }
+}
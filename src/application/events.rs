@@ -0,0 +1,80 @@
+//! Application event bus
+//!
+//! Application services drain the events recorded by aggregates after each
+//! state change and publish them here. Consumers such as the CDR writer,
+//! the WebSocket broadcaster and the audit log subscribe instead of being
+//! called from the SIP layer.
+//!
+//! Events of one aggregate are published in the order they were recorded,
+//! each stamped with the next number of that aggregate's sequence, so a
+//! subscriber can tell a gap (lagging receiver) from reordering.
+
+use crate::domain::call::CallEvent;
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use tokio::sync::broadcast;
+use uuid::Uuid;
+
+/// Call event with delivery metadata
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct EventEnvelope {
+    pub event_id: Uuid,
+    pub occurred_at: DateTime<Utc>,
+    /// Id of the `Call` aggregate (also the id of the call's CDR)
+    pub aggregate_id: Uuid,
+    /// SIP Call-ID of the call
+    pub call_id: String,
+    pub tenant_id: Option<Uuid>,
+    /// Position in the aggregate's event stream, starting at 1
+    pub sequence: u64,
+    pub event: CallEvent,
+}
+
+impl EventEnvelope {
+    pub fn new(call_id: String, tenant_id: Option<Uuid>, sequence: u64, event: CallEvent) -> Self {
+        let metadata = match &event {
+            CallEvent::Initiated(e) => &e.base.metadata,
+            CallEvent::Ringing(e) => &e.base.metadata,
+            CallEvent::EarlyMedia(e) => &e.base.metadata,
+            CallEvent::Answered(e) => &e.base.metadata,
+            CallEvent::Acknowledged(e) => &e.base.metadata,
+            CallEvent::Held(e) => &e.base.metadata,
+            CallEvent::Resumed(e) => &e.base.metadata,
+            CallEvent::Ended(e) => &e.base.metadata,
+        };
+        Self {
+            event_id: metadata.event_id,
+            occurred_at: metadata.occurred_at,
+            aggregate_id: event.call_id().as_uuid(),
+            call_id,
+            tenant_id,
+            sequence,
+            event,
+        }
+    }
+
+    /// Event type name (`call.answered`)
+    pub fn event_type(&self) -> &'static str {
+        match &self.event {
+            CallEvent::Initiated(_) => "call.initiated",
+            CallEvent::Ringing(_) => "call.ringing",
+            CallEvent::EarlyMedia(_) => "call.early_media",
+            CallEvent::Answered(_) => "call.answered",
+            CallEvent::Acknowledged(_) => "call.acknowledged",
+            CallEvent::Held(_) => "call.held",
+            CallEvent::Resumed(_) => "call.resumed",
+            CallEvent::Ended(_) => "call.ended",
+        }
+    }
+}
+
+/// Publishes domain events to subscribers
+#[async_trait]
+pub trait EventBus: Send + Sync {
+    /// Publish events of one aggregate, in order
+    async fn publish(&self, events: Vec<EventEnvelope>) -> Result<(), String>;
+
+    /// Receive every event published after subscribing
+    fn subscribe(&self) -> broadcast::Receiver<EventEnvelope>;
+}
@@ -8,7 +8,14 @@
 //! - Converting between domain models and DTOs
 
 pub mod call;
+pub mod campaign;
+pub mod chat;
+pub mod events;
+pub mod privacy;
+pub mod queue;
 pub mod registration;
+pub mod scheduled_call;
 pub mod session;
+pub mod voicemail;
 
 // Placeholder modules
@@ -0,0 +1,320 @@
+//! Erasure of a data subject's personal data
+//!
+//! The service runs an erasure request over every registered
+//! [`SubjectStore`]: records older than the retention minimum have the
+//! subject replaced with their pseudonym, newer ones are reported as
+//! retained. A dry run reports the same counts without changing anything.
+
+use crate::domain::privacy::{
+    ErasureReport, ErasureSubject, PrivacyConfig, Pseudonymizer, StoreErasure, SubjectStore,
+};
+use crate::domain::shared::SecretBox;
+use crate::domain::user::UserRepository;
+use chrono::Utc;
+use metrics::counter;
+use std::sync::Arc;
+use thiserror::Error;
+use tracing::{info, warn};
+use uuid::Uuid;
+
+#[derive(Debug, Error)]
+pub enum ErasureError {
+    #[error("Erasure not configured: no pseudonym secret")]
+    NotConfigured,
+    #[error("Invalid subject: {0}")]
+    InvalidSubject(String),
+    #[error("User {0} not found")]
+    UserNotFound(i32),
+    #[error("Erasure in {store} failed: {error}")]
+    Store { store: String, error: String },
+}
+
+/// Who to erase, and how
+#[derive(Debug, Clone, Default)]
+pub struct ErasureRequest {
+    /// Number, SIP/tel URI or username
+    pub subject: Option<String>,
+    /// A user, also known by their display name
+    pub user_id: Option<i32>,
+    pub requested_by: String,
+    pub dry_run: bool,
+    /// Delete the subject's voicemail and recordings as well
+    pub delete_media: bool,
+}
+
+/// Runs erasure requests over the stores holding personal data
+pub struct AnonymizationService {
+    config: PrivacyConfig,
+    stores: Vec<Arc<dyn SubjectStore>>,
+    users: Option<Arc<dyn UserRepository>>,
+}
+
+impl AnonymizationService {
+    pub fn new(config: PrivacyConfig) -> Self {
+        Self {
+            config,
+            stores: Vec::new(),
+            users: None,
+        }
+    }
+
+    pub fn with_store(mut self, store: Arc<dyn SubjectStore>) -> Self {
+        self.stores.push(store);
+        self
+    }
+
+    /// Resolves requests naming a user ID
+    pub fn with_user_repository(mut self, users: Arc<dyn UserRepository>) -> Self {
+        self.users = Some(users);
+        self
+    }
+
+    /// Registered stores, in erasure order
+    pub fn stores(&self) -> &[Arc<dyn SubjectStore>] {
+        &self.stores
+    }
+
+    async fn subject(&self, request: &ErasureRequest) -> Result<ErasureSubject, ErasureError> {
+        let Some(user_id) = request.user_id else {
+            let identifier = request.subject.as_deref().unwrap_or_default();
+            return ErasureSubject::new(identifier).map_err(ErasureError::InvalidSubject);
+        };
+        let user = match &self.users {
+            Some(users) => users
+                .find_by_id(user_id)
+                .await
+                .map_err(|e| ErasureError::Store {
+                    store: "users".to_string(),
+                    error: e.to_string(),
+                })?
+                .ok_or(ErasureError::UserNotFound(user_id))?,
+            None => return Err(ErasureError::UserNotFound(user_id)),
+        };
+        let mut subject =
+            ErasureSubject::new(&user.username).map_err(ErasureError::InvalidSubject)?;
+        if let Some(name) = &user.display_name {
+            subject = subject.with_display_name(name);
+        }
+        if let Some(alias) = &request.subject {
+            subject = subject.with_alias(alias);
+        }
+        Ok(subject)
+    }
+
+    /// Erase the subject of `request` from every store; the report is signed
+    pub async fn erase(&self, request: &ErasureRequest) -> Result<ErasureReport, ErasureError> {
+        let secret = self
+            .config
+            .pseudonym_secret
+            .as_ref()
+            .map(SecretBox::expose)
+            .ok_or(ErasureError::NotConfigured)?;
+        let pseudonyms = Pseudonymizer::new(secret);
+        let subject = self.subject(request).await?;
+        let started_at = Utc::now();
+        let cutoff = self.config.cutoff(started_at);
+
+        let mut stores = Vec::with_capacity(self.stores.len());
+        for store in &self.stores {
+            let failed = |error: String| ErasureError::Store {
+                store: store.name().to_string(),
+                error,
+            };
+            let (erasable, retained): (Vec<_>, Vec<_>) = store
+                .find_subject(&subject)
+                .await
+                .map_err(failed)?
+                .into_iter()
+                .partition(|record| record.at < cutoff);
+
+            let mut erasure = StoreErasure {
+                store: store.name().to_string(),
+                fields: store.subject_fields().iter().map(|f| f.to_string()).collect(),
+                found: (erasable.len() + retained.len()) as u64,
+                retained: retained.len() as u64,
+                record_ids: erasable.iter().map(|r| r.record_id.clone()).collect(),
+                ..Default::default()
+            };
+            if request.dry_run {
+                erasure.pseudonymized = erasable.len() as u64;
+                if request.delete_media {
+                    erasure.media_deleted =
+                        erasable.iter().filter(|r| r.media.is_some()).count() as u64;
+                }
+            } else {
+                if request.delete_media {
+                    erasure.media_deleted = store.erase_media(&erasable).await.map_err(failed)?;
+                }
+                erasure.pseudonymized = store
+                    .pseudonymize(&subject, &pseudonyms, &erasable)
+                    .await
+                    .map_err(failed)?;
+                counter!("privacy_erasure_records_total", "store" => store.name())
+                    .increment(erasure.pseudonymized);
+            }
+            if erasure.retained > 0 {
+                warn!(
+                    "Erasure kept {} records in {} under the retention minimum",
+                    erasure.retained, erasure.store
+                );
+            }
+            stores.push(erasure);
+        }
+
+        let mut report = ErasureReport {
+            id: Uuid::new_v4(),
+            subject: pseudonyms.pseudonym(&subject),
+            requested_by: request.requested_by.clone(),
+            dry_run: request.dry_run,
+            media_deleted: request.delete_media,
+            cutoff,
+            stores,
+            started_at,
+            finished_at: Utc::now(),
+            signature: String::new(),
+        };
+        report.sign(self.config.report_secret.as_ref().map_or(secret, SecretBox::expose));
+        info!(
+            "Erasure {} of {} by {}: {} records{}",
+            report.id,
+            report.subject,
+            report.requested_by,
+            report.pseudonymized(),
+            if report.dry_run { " (dry run)" } else { "" }
+        );
+        Ok(report)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::domain::billing::{
+        BillingAccount, BillingCycle, BillingManager, Currency, Rate, RatePlan, UsageType,
+    };
+    use crate::domain::cdr::{CallDetailRecord, CallDirection, CdrRepository};
+    use crate::domain::instant_messaging::{InstantMessage, MessageContentType, MessageRepository};
+    use crate::infrastructure::audit::logger::{AuditBackend, MemoryAuditBackend};
+    use crate::infrastructure::audit::{AuditEvent, AuditEventType, AuditLevel};
+    use crate::infrastructure::persistence::memory::{
+        MemoryCdrRepository, MemoryMessageRepository,
+    };
+    use chrono::Duration;
+
+    fn call(caller: &str, callee: &str, days_ago: i64) -> CallDetailRecord {
+        let mut cdr = CallDetailRecord::new(
+            Uuid::new_v4().to_string(),
+            caller.to_string(),
+            format!("sip:{}@example.com", caller),
+            "192.0.2.1".to_string(),
+            callee.to_string(),
+            format!("sip:{}@example.com", callee),
+            CallDirection::Outbound,
+        );
+        cdr.start_time = Utc::now() - Duration::days(days_ago);
+        cdr.mark_answered();
+        cdr.call_duration = Some(300);
+        cdr
+    }
+
+    fn billed(cdrs: &[CallDetailRecord]) -> Option<f64> {
+        let manager = BillingManager::new();
+        let plan = RatePlan::new("Plan".to_string(), Currency::USD, BillingCycle::Monthly)
+            .add_rate(Rate::new(UsageType::OutboundMinutes, 0.10));
+        let plan_id = manager.create_rate_plan(plan);
+        let account_id = manager.create_account(BillingAccount::new(
+            Uuid::new_v4(),
+            plan_id,
+            Currency::USD,
+            "billing@example.com".to_string(),
+        ));
+        for cdr in cdrs {
+            manager.record_call(account_id, cdr).unwrap();
+        }
+        manager.get_account_balance(&account_id)
+    }
+
+    #[tokio::test]
+    async fn test_erasure_pseudonymizes_every_store() {
+        let cdrs = Arc::new(MemoryCdrRepository::new());
+        cdrs.create(&call("alice", "bob", 40)).await.unwrap();
+        cdrs.create(&call("bob", "alice", 30)).await.unwrap();
+        cdrs.create(&call("alice", "carol", 1)).await.unwrap();
+        cdrs.create(&call("bob", "carol", 40)).await.unwrap();
+        let messages = Arc::new(MemoryMessageRepository::new());
+        let mut message = InstantMessage::new(
+            "sip:alice@example.com".to_string(),
+            "sip:bob@example.com".to_string(),
+            b"hi".to_vec(),
+            MessageContentType::TextPlain,
+        );
+        message.timestamp = Utc::now() - Duration::days(60);
+        messages.save(&message).await.unwrap();
+        let audit = Arc::new(MemoryAuditBackend::new(100));
+        let mut event = AuditEvent::new(
+            AuditLevel::Info,
+            AuditEventType::AuthenticationSuccess {
+                username: "alice".to_string(),
+                method: "digest".to_string(),
+            },
+        );
+        event.timestamp = Utc::now() - Duration::days(60);
+        audit.log(&event).await.unwrap();
+
+        let service = AnonymizationService::new(PrivacyConfig {
+            pseudonym_secret: Some(SecretBox::new("a very secret pseudonym key")),
+            report_secret: Some(SecretBox::new("a very secret report key")),
+            retention_minimum_days: 7,
+        })
+        .with_store(cdrs.clone())
+        .with_store(messages.clone())
+        .with_store(audit.clone());
+        let before = billed(&cdrs.all());
+        let request = ErasureRequest {
+            subject: Some("sip:alice@example.com".to_string()),
+            requested_by: "admin".to_string(),
+            dry_run: true,
+            ..Default::default()
+        };
+
+        let dry_run = service.erase(&request).await.unwrap();
+        assert_eq!(dry_run.pseudonymized(), 4);
+        assert_eq!(dry_run.stores[0].retained, 1);
+        assert_eq!(cdrs.all()[0].caller_username, "alice");
+
+        let report = service
+            .erase(&ErasureRequest {
+                dry_run: false,
+                ..request.clone()
+            })
+            .await
+            .unwrap();
+        assert_eq!(report.pseudonymized(), 4);
+        assert_eq!(report.subject, dry_run.subject);
+        assert!(report.verify("a very secret report key"));
+        assert!(!report.verify("a very secret pseudonym key"));
+
+        let pseudonym = &report.subject;
+        let all = cdrs.all();
+        assert_eq!(&all[0].caller_username, pseudonym);
+        assert_eq!(all[0].caller_uri, format!("sip:{}@example.com", pseudonym));
+        assert_eq!(&all[1].callee_username, pseudonym);
+        assert_eq!(all[1].caller_username, "bob");
+        // Under the retention minimum
+        assert_eq!(all[2].caller_username, "alice");
+        assert_eq!(billed(&all), before);
+
+        let message = messages.get_by_id(message.id).await.unwrap().unwrap();
+        assert_eq!(message.from, format!("sip:{}@example.com", pseudonym));
+        let events = audit.query(Default::default()).await.unwrap();
+        assert!(matches!(
+            &events[0].event_type,
+            AuditEventType::AuthenticationSuccess { username, .. } if username == pseudonym
+        ));
+
+        // Nothing left but the retained call; the pseudonym is the same again
+        let again = service.erase(&request).await.unwrap();
+        assert_eq!(again.pseudonymized(), 0);
+        assert_eq!(&again.subject, pseudonym);
+    }
+}
@@ -0,0 +1,227 @@
+//! Queue agent availability
+//!
+//! Agents whose device stops responding would keep being offered calls that
+//! ring into nothing. This takes them out of their queues (paused or logged
+//! out, per queue) when:
+//! - all of their registrations expired
+//! - their device stopped answering keepalives
+//! - they let `max_missed_offers` offers in a row go unanswered (see
+//!   `CallQueueEngine::offer_missed`)
+//!
+//! DND-linked agents are paused in every queue while DND is on. Automatic
+//! changes can be sent to a supervisor through a [`SupervisorNotifier`].
+
+use crate::domain::call_queue::{AgentStateChange, AgentStateReason};
+use crate::domain::call_queue_engine::CallQueueEngine;
+use crate::domain::dnd::DndManager;
+use crate::infrastructure::protocols::sip::registration_events::aor_user;
+use crate::infrastructure::protocols::sip::{Registrar, RegistrationEvent, RegistrationEventType};
+use async_trait::async_trait;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::broadcast;
+use tokio::task::JoinHandle;
+use tracing::{error, info, warn};
+
+/// Tells a supervisor about agents taken out of queues automatically
+#[async_trait]
+pub trait SupervisorNotifier: Send + Sync {
+    async fn notify(&self, change: &AgentStateChange) -> Result<(), String>;
+}
+
+/// Pauses or logs out queue agents that stopped responding
+pub struct AgentAvailabilityService {
+    engine: Arc<CallQueueEngine>,
+}
+
+impl AgentAvailabilityService {
+    pub fn new(engine: Arc<CallQueueEngine>) -> Self {
+        Self { engine }
+    }
+
+    /// The device of `username` stopped answering keepalives
+    pub fn keepalive_failed(&self, username: &str) -> Vec<AgentStateChange> {
+        self.unreachable(username, AgentStateReason::KeepaliveFailed)
+    }
+
+    /// All registrations of `username` expired
+    pub fn registration_expired(&self, username: &str) -> Vec<AgentStateChange> {
+        self.unreachable(username, AgentStateReason::RegistrationExpired)
+    }
+
+    fn unreachable(&self, username: &str, reason: AgentStateReason) -> Vec<AgentStateChange> {
+        let changes = self.engine.agent_unreachable(username, reason);
+        for change in &changes {
+            info!(
+                "Agent {} {:?} in queue {} ({})",
+                username,
+                change.to,
+                change.queue_id,
+                reason.as_str()
+            );
+        }
+        changes
+    }
+
+    /// Handle a registrar event; agents are only unreachable once their
+    /// last binding expired
+    pub async fn on_registration_event(
+        &self,
+        registrar: &Registrar,
+        event: &RegistrationEvent,
+    ) -> Vec<AgentStateChange> {
+        if event.event_type != RegistrationEventType::Expired
+            || registrar.is_registered(&event.aor).await
+        {
+            return Vec::new();
+        }
+        match aor_user(&event.aor) {
+            Some(username) => self.registration_expired(username),
+            None => Vec::new(),
+        }
+    }
+
+    /// Follow the registrar, purging expired bindings every `sweep_interval`
+    /// so expirations are noticed without a lookup
+    pub fn watch_registrations(
+        self: Arc<Self>,
+        registrar: Arc<Registrar>,
+        sweep_interval: Duration,
+    ) -> JoinHandle<()> {
+        let mut rx = registrar.subscribe_events();
+        tokio::spawn(async move {
+            let mut ticker = tokio::time::interval(sweep_interval);
+            loop {
+                tokio::select! {
+                    _ = ticker.tick() => {
+                        registrar.purge_expired().await;
+                    }
+                    event = rx.recv() => match event {
+                        Ok(event) => {
+                            self.on_registration_event(&registrar, &event).await;
+                        }
+                        Err(broadcast::error::RecvError::Lagged(skipped)) => {
+                            warn!("Dropped {} registration events (agent availability lagging)", skipped);
+                        }
+                        Err(broadcast::error::RecvError::Closed) => break,
+                    },
+                }
+            }
+        })
+    }
+
+    /// Pause DND-linked agents while their DND is on
+    pub fn watch_dnd(self: Arc<Self>, dnd: &DndManager) -> JoinHandle<()> {
+        let mut rx = dnd.subscribe();
+        tokio::spawn(async move {
+            loop {
+                match rx.recv().await {
+                    Ok(change) => {
+                        self.engine.set_agent_dnd(&change.user_id, change.enabled);
+                    }
+                    Err(broadcast::error::RecvError::Lagged(skipped)) => {
+                        warn!(
+                            "Dropped {} DND changes (agent availability lagging)",
+                            skipped
+                        );
+                    }
+                    Err(broadcast::error::RecvError::Closed) => break,
+                }
+            }
+        })
+    }
+}
+
+/// Send automatic agent state changes to `notifier`
+pub fn spawn_supervisor_notifier(
+    engine: &CallQueueEngine,
+    notifier: Arc<dyn SupervisorNotifier>,
+) -> JoinHandle<()> {
+    let mut rx = engine.subscribe_agent_states();
+    tokio::spawn(async move {
+        loop {
+            match rx.recv().await {
+                Ok(change) if change.reason.is_automatic() => {
+                    if let Err(e) = notifier.notify(&change).await {
+                        error!(
+                            "Failed to notify supervisor about agent {}: {}",
+                            change.username, e
+                        );
+                    }
+                }
+                Ok(_) => {}
+                Err(broadcast::error::RecvError::Lagged(skipped)) => {
+                    warn!(
+                        "Dropped {} agent state changes (supervisor notifier lagging)",
+                        skipped
+                    );
+                }
+                Err(broadcast::error::RecvError::Closed) => break,
+            }
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::domain::call_queue::{AgentAvailability, CallQueue, QueueMember, QueueStrategy};
+    use crate::infrastructure::protocols::sip::registrar::Binding;
+    use chrono::Utc;
+
+    #[tokio::test]
+    async fn test_registration_expiry_pauses_agent() {
+        let engine = Arc::new(CallQueueEngine::new());
+        let queue = CallQueue::new(
+            "Support".to_string(),
+            "8000".to_string(),
+            QueueStrategy::Linear,
+        );
+        engine.start_queue(queue.clone());
+        let member = QueueMember::new(7, "alice".to_string(), "1001".to_string());
+        let member_id = member.id;
+        engine.add_member(queue.id, member).unwrap();
+
+        let registrar = Registrar::new();
+        let expired = |contact: &str| Binding {
+            contact: contact.to_string(),
+            expires_at: Utc::now() - chrono::Duration::seconds(1),
+            user_agent: None,
+        };
+        let live = Binding {
+            contact: "sip:alice@10.0.0.2".to_string(),
+            expires_at: Utc::now() + chrono::Duration::seconds(3600),
+            user_agent: None,
+        };
+        registrar
+            .replace_registration(
+                "sip:alice@example.com",
+                vec![expired("sip:alice@10.0.0.1"), live],
+            )
+            .await;
+        let mut rx = registrar.subscribe_events();
+        let service = AgentAvailabilityService::new(engine.clone());
+
+        // Another binding is still registered
+        registrar.purge_expired().await;
+        let event = rx.recv().await.unwrap();
+        assert!(service
+            .on_registration_event(&registrar, &event)
+            .await
+            .is_empty());
+
+        registrar
+            .replace_registration("sip:alice@example.com", vec![expired("sip:alice@10.0.0.2")])
+            .await;
+        registrar.purge_expired().await;
+        let event = rx.recv().await.unwrap();
+        let changes = service.on_registration_event(&registrar, &event).await;
+
+        assert_eq!(changes.len(), 1);
+        assert_eq!(changes[0].member_id, member_id);
+        assert_eq!(changes[0].to, AgentAvailability::Paused);
+        assert_eq!(changes[0].reason, AgentStateReason::RegistrationExpired);
+        assert_eq!(engine.get_available_agents(queue.id).unwrap(), 0);
+        assert_eq!(engine.agent_state_history(7), changes);
+    }
+}
@@ -0,0 +1,333 @@
+//! Queue callbacks
+//!
+//! A caller who accepted a callback (see `CallbackOffer`) keeps a place in
+//! the queue engine. When that place is due and an agent is reserved, the
+//! caller is called back, hears "this is your callback" and is connected to
+//! the agent. The original call and every callback attempt get CDRs linked
+//! by the callback's correlation ID.
+
+use crate::domain::call_queue::{QueueCallback, QueueMember};
+use crate::domain::call_queue_engine::{CallQueueEngine, QueueEngineError};
+use crate::domain::cdr::{CallDetailRecord, CallDirection, CallStatus, CdrRepository};
+use crate::domain::queue_callback::CallbackPrompt;
+use async_trait::async_trait;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::task::JoinHandle;
+use tracing::{error, info, warn};
+use uuid::Uuid;
+
+/// Places and connects callback calls
+#[async_trait]
+pub trait CallbackDialer: Send + Sync {
+    /// Call `number` as `call_id`, presenting `caller_id`; returns once answered
+    async fn dial(&self, call_id: &str, number: &str, caller_id: &str) -> Result<(), String>;
+
+    /// Play a prompt on an answered call
+    async fn play(&self, call_id: &str, prompt: &CallbackPrompt) -> Result<(), String>;
+
+    /// Connect an answered call to the agent
+    async fn connect(&self, call_id: &str, agent: &QueueMember) -> Result<(), String>;
+}
+
+/// Requests and dials queue callbacks
+pub struct QueueCallbackService {
+    engine: Arc<CallQueueEngine>,
+    dialer: Arc<dyn CallbackDialer>,
+    cdr_repository: Option<Arc<dyn CdrRepository>>,
+}
+
+impl QueueCallbackService {
+    pub fn new(engine: Arc<CallQueueEngine>, dialer: Arc<dyn CallbackDialer>) -> Self {
+        Self {
+            engine,
+            dialer,
+            cdr_repository: None,
+        }
+    }
+
+    /// Record callback legs in `repository`
+    pub fn with_cdr_repository(mut self, repository: Arc<dyn CdrRepository>) -> Self {
+        self.cdr_repository = Some(repository);
+        self
+    }
+
+    /// Hold the place of the caller of `call_id` for a callback to `number`
+    ///
+    /// The caller's call can be released once this returns.
+    pub async fn request(
+        &self,
+        queue_id: Uuid,
+        call_id: &str,
+        number: String,
+    ) -> Result<QueueCallback, QueueEngineError> {
+        let callback = self.engine.request_callback(queue_id, call_id, number)?;
+        info!(
+            "Callback {} requested by call {} to {}",
+            callback.id, call_id, callback.number
+        );
+
+        if let Some(repository) = &self.cdr_repository {
+            match repository.get_by_call_id(call_id).await {
+                Ok(Some(mut cdr)) => {
+                    cdr.set_correlation_id(callback.correlation_id());
+                    if let Err(e) = repository.update(&cdr).await {
+                        error!("Failed to link CDR of call {} to callback: {}", call_id, e);
+                    }
+                }
+                Ok(None) => {}
+                Err(e) => error!("Failed to get CDR of call {}: {}", call_id, e),
+            }
+        }
+
+        Ok(callback)
+    }
+
+    /// Dial the queue's due callback, if any; returns it after the attempt
+    pub async fn run_due(&self, queue_id: Uuid) -> Result<Option<QueueCallback>, QueueEngineError> {
+        let (callback, agent) = match self.engine.next_due_callback(queue_id)? {
+            Some(due) => due,
+            None => return Ok(None),
+        };
+        let queue = self.engine.get_queue(queue_id)?;
+        let call_id = format!("{}-{}", callback.correlation_id(), callback.attempts);
+        info!(
+            "Calling back {} for queue {} (attempt {}/{})",
+            callback.number, queue.name, callback.attempts, callback.max_attempts
+        );
+
+        let mut cdr = CallDetailRecord::new(
+            call_id.clone(),
+            queue.extension.clone(),
+            format!("sip:{}", queue.extension),
+            "0.0.0.0".to_string(),
+            callback.number.clone(),
+            format!("sip:{}", callback.number),
+            CallDirection::Outbound,
+        );
+        cdr.set_correlation_id(callback.correlation_id());
+        self.save_cdr(&cdr, true).await;
+
+        let result = match self
+            .dialer
+            .dial(&call_id, &callback.number, &queue.extension)
+            .await
+        {
+            Ok(()) => {
+                cdr.mark_answered();
+                match self
+                    .dialer
+                    .play(&call_id, &CallbackPrompt::ThisIsYourCallback)
+                    .await
+                {
+                    Ok(()) => self.dialer.connect(&call_id, &agent).await,
+                    Err(e) => Err(e),
+                }
+            }
+            Err(e) => Err(e),
+        };
+
+        let callback = match result {
+            Ok(()) => {
+                self.save_cdr(&cdr, false).await;
+                self.engine
+                    .callback_connected(queue_id, callback.id, &call_id, agent.id)?
+            }
+            Err(reason) => {
+                warn!(
+                    "Callback {} to {} failed: {}",
+                    callback.id, callback.number, reason
+                );
+                let status = if cdr.answer_time.is_some() {
+                    CallStatus::Failed
+                } else {
+                    CallStatus::NoAnswer
+                };
+                cdr.mark_ended(status, Some(reason.clone()), None);
+                self.save_cdr(&cdr, false).await;
+                self.engine
+                    .callback_failed(queue_id, callback.id, &call_id, agent.id, &reason)?
+            }
+        };
+
+        Ok(Some(callback))
+    }
+
+    async fn save_cdr(&self, cdr: &CallDetailRecord, create: bool) {
+        if let Some(repository) = &self.cdr_repository {
+            let result = if create {
+                repository.create(cdr).await
+            } else {
+                repository.update(cdr).await
+            };
+            if let Err(e) = result {
+                error!("Failed to save CDR of callback call {}: {}", cdr.call_id, e);
+            }
+        }
+    }
+}
+
+/// Dial due callbacks of every queue each `interval`
+pub fn spawn_callback_runner(
+    service: Arc<QueueCallbackService>,
+    interval: Duration,
+) -> JoinHandle<()> {
+    tokio::spawn(async move {
+        let mut ticker = tokio::time::interval(interval);
+        loop {
+            ticker.tick().await;
+            for queue_id in service.engine.queue_ids() {
+                // Until no callback is due or no agent is free
+                loop {
+                    match service.run_due(queue_id).await {
+                        Ok(Some(_)) => {}
+                        Ok(None) => break,
+                        Err(e) => {
+                            error!("Failed to run callbacks of queue {}: {}", queue_id, e);
+                            break;
+                        }
+                    }
+                }
+            }
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::domain::call_queue::{CallQueue, CallbackStatus, QueueStrategy};
+    use crate::domain::cdr::MockCdrRepository;
+    use crate::domain::queue_callback::CallbackOffer;
+    use std::sync::Mutex;
+
+    /// Fails the first `failures` dials, answers the rest
+    #[derive(Default)]
+    struct FakeDialer {
+        failures: Mutex<u32>,
+        dialed: Mutex<Vec<String>>,
+        played: Mutex<Vec<CallbackPrompt>>,
+        connected: Mutex<Vec<String>>,
+    }
+
+    #[async_trait]
+    impl CallbackDialer for FakeDialer {
+        async fn dial(&self, _call_id: &str, number: &str, _caller_id: &str) -> Result<(), String> {
+            self.dialed.lock().unwrap().push(number.to_string());
+            let mut failures = self.failures.lock().unwrap();
+            if *failures > 0 {
+                *failures -= 1;
+                return Err("No answer".to_string());
+            }
+            Ok(())
+        }
+
+        async fn play(&self, _call_id: &str, prompt: &CallbackPrompt) -> Result<(), String> {
+            self.played.lock().unwrap().push(prompt.clone());
+            Ok(())
+        }
+
+        async fn connect(&self, _call_id: &str, agent: &QueueMember) -> Result<(), String> {
+            self.connected.lock().unwrap().push(agent.extension.clone());
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn test_failed_then_successful_callback() {
+        let engine = Arc::new(CallQueueEngine::new());
+        let mut queue = CallQueue::new(
+            "Support".to_string(),
+            "5000".to_string(),
+            QueueStrategy::Linear,
+        );
+        queue.callback_digit = Some('9');
+        queue.callback_retry_delay = Duration::from_secs(0);
+        engine.start_queue(queue.clone());
+
+        let caller = "sip:+15550100@example.com";
+        engine
+            .enqueue_call(queue.id, "call-1".to_string(), caller.to_string(), None)
+            .unwrap();
+
+        let saved = Arc::new(Mutex::new(Vec::<CallDetailRecord>::new()));
+        let mut cdrs = MockCdrRepository::new();
+        cdrs.expect_get_by_call_id().returning(|call_id| {
+            Ok(Some(CallDetailRecord::new(
+                call_id.to_string(),
+                "15550100".to_string(),
+                "sip:+15550100@example.com".to_string(),
+                "192.0.2.10".to_string(),
+                "5000".to_string(),
+                "sip:5000@example.com".to_string(),
+                CallDirection::Inbound,
+            )))
+        });
+        let created = saved.clone();
+        cdrs.expect_create().returning(move |cdr| {
+            created.lock().unwrap().push(cdr.clone());
+            Ok(())
+        });
+        let updated = saved.clone();
+        cdrs.expect_update().returning(move |cdr| {
+            let mut saved = updated.lock().unwrap();
+            saved.retain(|c| c.id != cdr.id);
+            saved.push(cdr.clone());
+            Ok(())
+        });
+
+        let dialer = Arc::new(FakeDialer {
+            failures: Mutex::new(1),
+            ..Default::default()
+        });
+        let service = QueueCallbackService::new(engine.clone(), dialer.clone())
+            .with_cdr_repository(Arc::new(cdrs));
+
+        // Caller presses the callback digit and accepts the caller ID
+        assert!(engine.is_callback_digit(queue.id, '9'));
+        let (mut offer, _) = CallbackOffer::start(caller);
+        offer.handle_digit('1');
+        let number = offer.accepted_number().unwrap().to_string();
+        let callback = service.request(queue.id, "call-1", number).await.unwrap();
+
+        // No agent yet: nothing is dialed
+        assert!(service.run_due(queue.id).await.unwrap().is_none());
+        engine
+            .add_member(
+                queue.id,
+                QueueMember::new(3, "agent".to_string(), "1001".to_string()),
+            )
+            .unwrap();
+
+        let first = service.run_due(queue.id).await.unwrap().unwrap();
+        assert_eq!(first.status, CallbackStatus::Pending);
+        assert_eq!(first.last_failure.as_deref(), Some("No answer"));
+
+        let second = service.run_due(queue.id).await.unwrap().unwrap();
+        assert_eq!(second.status, CallbackStatus::Connected);
+        assert_eq!(second.attempts, 2);
+
+        assert_eq!(
+            *dialer.dialed.lock().unwrap(),
+            vec!["+15550100", "+15550100"]
+        );
+        assert_eq!(
+            *dialer.played.lock().unwrap(),
+            vec![CallbackPrompt::ThisIsYourCallback]
+        );
+        assert_eq!(*dialer.connected.lock().unwrap(), vec!["1001"]);
+        assert!(engine.list_callbacks(queue.id).unwrap().is_empty());
+
+        // Original call and both attempts share the correlation ID
+        let saved = saved.lock().unwrap();
+        assert_eq!(saved.len(), 3);
+        assert!(saved
+            .iter()
+            .all(|cdr| cdr.correlation_id.as_deref() == Some(callback.correlation_id().as_str())));
+        let attempts: Vec<&CallDetailRecord> =
+            saved.iter().filter(|cdr| cdr.call_id != "call-1").collect();
+        assert_eq!(attempts[0].status, CallStatus::NoAnswer);
+        assert_eq!(attempts[1].status, CallStatus::Active);
+        assert!(attempts[1].answer_time.is_some());
+    }
+}
@@ -0,0 +1,9 @@
+//! Call queue application services
+
+pub mod agent_availability;
+pub mod callback;
+pub mod overflow;
+
+pub use agent_availability::{spawn_supervisor_notifier, AgentAvailabilityService, SupervisorNotifier};
+pub use callback::{spawn_callback_runner, CallbackDialer, QueueCallbackService};
+pub use overflow::{spawn_overflow_monitor, OverflowTransfer, QueueOverflowService};
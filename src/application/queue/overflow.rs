@@ -0,0 +1,251 @@
+//! Queue overflow
+//!
+//! A queue's overflow rules (see `OverflowRule`) are checked when a call
+//! arrives and again while it waits. A call a rule applies to hears the
+//! rule's announcement, if any, and is transferred to the rule's
+//! destination; its CDR gets the `overflowed` disposition.
+
+use crate::domain::call_queue::{OverflowDestination, OverflowRule};
+use crate::domain::call_queue_engine::{CallQueueEngine, QueueArrival, QueueEngineError};
+use crate::domain::cdr::CdrRepository;
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::task::JoinHandle;
+use tracing::{error, info, warn};
+use uuid::Uuid;
+
+/// Plays announcements to and transfers overflowing calls
+#[async_trait]
+pub trait OverflowTransfer: Send + Sync {
+    /// Play an announcement on the call
+    async fn play(&self, call_id: &str, announcement: &str) -> Result<(), String>;
+
+    /// Transfer the call to `destination`
+    async fn transfer(&self, call_id: &str, destination: &OverflowDestination)
+        -> Result<(), String>;
+}
+
+/// Sends calls to the destination of the queue's overflow rules
+pub struct QueueOverflowService {
+    engine: Arc<CallQueueEngine>,
+    transfer: Arc<dyn OverflowTransfer>,
+    cdr_repository: Option<Arc<dyn CdrRepository>>,
+}
+
+impl QueueOverflowService {
+    pub fn new(engine: Arc<CallQueueEngine>, transfer: Arc<dyn OverflowTransfer>) -> Self {
+        Self {
+            engine,
+            transfer,
+            cdr_repository: None,
+        }
+    }
+
+    /// Mark CDRs of overflowed calls in `repository`
+    pub fn with_cdr_repository(mut self, repository: Arc<dyn CdrRepository>) -> Self {
+        self.cdr_repository = Some(repository);
+        self
+    }
+
+    /// Enqueue a call arriving at `now`, or transfer it right away when an
+    /// overflow rule applies
+    pub async fn arrive(
+        &self,
+        queue_id: Uuid,
+        call_id: &str,
+        caller: String,
+        caller_name: Option<String>,
+        now: DateTime<Utc>,
+    ) -> Result<QueueArrival, QueueEngineError> {
+        let arrival = self
+            .engine
+            .arrive(queue_id, call_id.to_string(), caller, caller_name, now)?;
+        if let QueueArrival::Overflowed(rule) = &arrival {
+            self.overflow(queue_id, call_id, rule).await;
+        }
+        Ok(arrival)
+    }
+
+    /// Transfer the waiting callers an overflow rule applies to at `now`;
+    /// returns their call IDs
+    pub async fn run_due(
+        &self,
+        queue_id: Uuid,
+        now: DateTime<Utc>,
+    ) -> Result<Vec<String>, QueueEngineError> {
+        let overflows = self.engine.take_overflows(queue_id, now)?;
+        let mut call_ids = Vec::with_capacity(overflows.len());
+        for (call, rule) in overflows {
+            self.overflow(queue_id, &call.call_id, &rule).await;
+            call_ids.push(call.call_id);
+        }
+        Ok(call_ids)
+    }
+
+    async fn overflow(&self, queue_id: Uuid, call_id: &str, rule: &OverflowRule) {
+        info!(
+            "Call {} overflows queue {} to {}",
+            call_id, queue_id, rule.destination
+        );
+
+        if let Some(announcement) = &rule.announcement {
+            if let Err(e) = self.transfer.play(call_id, announcement).await {
+                warn!("Failed to play overflow announcement to {}: {}", call_id, e);
+            }
+        }
+        if let Err(e) = self.transfer.transfer(call_id, &rule.destination).await {
+            error!(
+                "Failed to transfer call {} to {}: {}",
+                call_id, rule.destination, e
+            );
+            return;
+        }
+
+        if let Some(repository) = &self.cdr_repository {
+            match repository.get_by_call_id(call_id).await {
+                Ok(Some(mut cdr)) => {
+                    cdr.mark_overflowed(rule.destination.to_string());
+                    if let Err(e) = repository.update(&cdr).await {
+                        error!("Failed to mark CDR of call {} overflowed: {}", call_id, e);
+                    }
+                }
+                Ok(None) => {}
+                Err(e) => error!("Failed to get CDR of call {}: {}", call_id, e),
+            }
+        }
+    }
+}
+
+/// Check the overflow rules of every queue's waiting callers each `interval`
+pub fn spawn_overflow_monitor(
+    service: Arc<QueueOverflowService>,
+    interval: Duration,
+) -> JoinHandle<()> {
+    tokio::spawn(async move {
+        let mut ticker = tokio::time::interval(interval);
+        loop {
+            ticker.tick().await;
+            for queue_id in service.engine.queue_ids() {
+                if let Err(e) = service.run_due(queue_id, Utc::now()).await {
+                    error!("Failed to check overflow of queue {}: {}", queue_id, e);
+                }
+            }
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::domain::call_queue::{CallQueue, OverflowCondition, QueueStrategy};
+    use crate::domain::cdr::{CallDetailRecord, CallDirection, CallStatus, MockCdrRepository};
+    use chrono::TimeZone;
+    use std::sync::Mutex;
+
+    #[derive(Default)]
+    struct FakeTransfer {
+        played: Mutex<Vec<String>>,
+        transferred: Mutex<Vec<(String, OverflowDestination)>>,
+    }
+
+    #[async_trait]
+    impl OverflowTransfer for FakeTransfer {
+        async fn play(&self, _call_id: &str, announcement: &str) -> Result<(), String> {
+            self.played.lock().unwrap().push(announcement.to_string());
+            Ok(())
+        }
+
+        async fn transfer(
+            &self,
+            call_id: &str,
+            destination: &OverflowDestination,
+        ) -> Result<(), String> {
+            self.transferred
+                .lock()
+                .unwrap()
+                .push((call_id.to_string(), destination.clone()));
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn test_waiting_call_overflows_to_voicemail() {
+        let engine = Arc::new(CallQueueEngine::new());
+        let mut queue = CallQueue::new(
+            "Support".to_string(),
+            "5000".to_string(),
+            QueueStrategy::RingAll,
+        );
+        queue.overflow_rules = vec![OverflowRule {
+            condition: OverflowCondition::WaitAbove { secs: 60 },
+            destination: OverflowDestination::Voicemail {
+                mailbox: "5000".to_string(),
+            },
+            announcement: Some("queue-overflow".to_string()),
+        }];
+        engine.start_queue(queue.clone());
+
+        let saved = Arc::new(Mutex::new(Vec::<CallDetailRecord>::new()));
+        let mut cdrs = MockCdrRepository::new();
+        cdrs.expect_get_by_call_id().returning(|call_id| {
+            Ok(Some(CallDetailRecord::new(
+                call_id.to_string(),
+                "15550100".to_string(),
+                "sip:+15550100@example.com".to_string(),
+                "192.0.2.10".to_string(),
+                "5000".to_string(),
+                "sip:5000@example.com".to_string(),
+                CallDirection::Inbound,
+            )))
+        });
+        let updated = saved.clone();
+        cdrs.expect_update().returning(move |cdr| {
+            updated.lock().unwrap().push(cdr.clone());
+            Ok(())
+        });
+
+        let transfer = Arc::new(FakeTransfer::default());
+        let service = QueueOverflowService::new(engine.clone(), transfer.clone())
+            .with_cdr_repository(Arc::new(cdrs));
+
+        let start = Utc.with_ymd_and_hms(2025, 11, 10, 10, 0, 0).unwrap();
+        let arrival = service
+            .arrive(
+                queue.id,
+                "call-1",
+                "sip:+15550100@example.com".to_string(),
+                None,
+                start,
+            )
+            .await
+            .unwrap();
+        assert!(matches!(arrival, QueueArrival::Queued(_)));
+
+        let later = start + chrono::Duration::seconds(30);
+        assert!(service.run_due(queue.id, later).await.unwrap().is_empty());
+
+        let later = start + chrono::Duration::seconds(61);
+        assert_eq!(service.run_due(queue.id, later).await.unwrap(), vec!["call-1"]);
+        assert_eq!(*transfer.played.lock().unwrap(), vec!["queue-overflow"]);
+        assert_eq!(
+            *transfer.transferred.lock().unwrap(),
+            vec![(
+                "call-1".to_string(),
+                OverflowDestination::Voicemail {
+                    mailbox: "5000".to_string()
+                }
+            )]
+        );
+        assert!(engine.get_waiting_calls(queue.id).unwrap().is_empty());
+
+        let saved = saved.lock().unwrap();
+        assert_eq!(saved.len(), 1);
+        assert_eq!(saved[0].status, CallStatus::Overflowed);
+        assert_eq!(
+            saved[0].end_reason.as_deref(),
+            Some("Queue overflow to voicemail 5000")
+        );
+    }
+}
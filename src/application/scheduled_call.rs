@@ -0,0 +1,757 @@
+//! Scheduled calls
+//!
+//! The service stores schedules in a repository, so they survive restarts,
+//! and a runner asks it every `poll_interval_secs` for the calls due. Each
+//! is placed through the campaign dialer (the same outbound path as
+//! notification campaigns) and its outcome recorded on the schedule. A
+//! finished occurrence is published to subscribers; a failed one is also
+//! POSTed to the webhook and emailed to the schedule's `notify_email`.
+//!
+//! `*77<HHMM>` sets a wake-up call for the caller at the next HH:MM in
+//! their tenant's zone.
+
+use crate::application::campaign::{CampaignDialer, DialAttempt};
+use crate::domain::campaign::{CampaignContent, RetryPolicy};
+use crate::domain::feature_code::{FeatureCall, FeatureCodeHandler, FeatureOutcome};
+use crate::domain::scheduled_call::{
+    CreateScheduledCall, ScheduledCall, ScheduledCallError, ScheduledCallRepository,
+    ScheduledCallStatus,
+};
+use crate::domain::shared::time_zone::parse_time_zone;
+use crate::domain::shared::TimeZoneConfig;
+use crate::infrastructure::clock::{system_clock, Clock};
+use crate::infrastructure::setup_latency::WebhookTarget;
+use crate::infrastructure::transcription::VoicemailEmailSender;
+use async_trait::async_trait;
+use chrono::{DateTime, Duration as ChronoDuration, NaiveDateTime, NaiveTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::sync::broadcast;
+use tokio::task::JoinHandle;
+use tracing::{error, info, warn};
+use uuid::Uuid;
+
+/// Scheduled call settings
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ScheduledCallConfig {
+    /// Number presented to the called extension
+    pub caller_id: String,
+    /// Prompt of schedules made without content
+    pub prompt: String,
+    /// Seconds an answered call is kept up for its content and the
+    /// confirmation digit
+    pub listen_secs: u64,
+    /// Retry policy of schedules made without one
+    pub retry: RetryPolicy,
+    /// Seconds between checks for due calls
+    pub poll_interval_secs: u64,
+    /// Calls due longer ago than this, e.g. while the PBX was down, are
+    /// failed as missed instead of placed late
+    pub max_lateness_secs: u64,
+    /// Failed calls are POSTed here as JSON (plain HTTP)
+    pub webhook_url: Option<String>,
+    pub webhook_timeout_ms: u64,
+    /// Digit confirming a wake-up call set with `*77`; none for no
+    /// confirmation
+    pub feature_confirm_digit: Option<char>,
+    /// Played after `*77` set a wake-up call
+    pub set_prompt: String,
+    /// Played when `*77` was dialed with an invalid time
+    pub invalid_prompt: String,
+}
+
+impl Default for ScheduledCallConfig {
+    fn default() -> Self {
+        Self {
+            caller_id: "wakeup".to_string(),
+            prompt: "wakeup-call".to_string(),
+            listen_secs: 30,
+            retry: RetryPolicy::default(),
+            poll_interval_secs: 10,
+            max_lateness_secs: 900,
+            webhook_url: None,
+            webhook_timeout_ms: 2000,
+            feature_confirm_digit: Some('1'),
+            set_prompt: "wakeup-call-set".to_string(),
+            invalid_prompt: "wakeup-call-invalid".to_string(),
+        }
+    }
+}
+
+impl ScheduledCallConfig {
+    pub fn validate(&self) -> Result<(), String> {
+        if self.caller_id.trim().is_empty() {
+            return Err("caller_id must not be empty".to_string());
+        }
+        if self.prompt.trim().is_empty() {
+            return Err("prompt must not be empty".to_string());
+        }
+        if self.poll_interval_secs == 0 {
+            return Err("poll_interval_secs must be at least 1".to_string());
+        }
+        if self.retry.max_attempts == 0 {
+            return Err("retry.max_attempts must be at least 1".to_string());
+        }
+        if let Some(digit) = self.feature_confirm_digit {
+            if !matches!(digit, '0'..='9' | '*' | '#') {
+                return Err(format!("feature_confirm_digit '{}' is not a DTMF digit", digit));
+            }
+        }
+        if let Some(url) = &self.webhook_url {
+            WebhookTarget::parse(url)?;
+        }
+        Ok(())
+    }
+}
+
+/// A finished occurrence of a scheduled call
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ScheduledCallEvent {
+    pub id: Uuid,
+    pub target: String,
+    pub realm: Option<String>,
+    /// Local time the call was scheduled for
+    pub local_time: NaiveDateTime,
+    pub time_zone: String,
+    pub outcome: ScheduledCallStatus,
+    pub attempts: u32,
+    /// Result of the last attempt, e.g. `No answer`
+    pub result: Option<String>,
+    pub timestamp: DateTime<Utc>,
+}
+
+/// Stores schedules and places the calls when due
+pub struct ScheduledCallService {
+    repository: Arc<dyn ScheduledCallRepository>,
+    config: ScheduledCallConfig,
+    time_zones: TimeZoneConfig,
+    clock: Arc<dyn Clock>,
+    dialer: Option<Arc<dyn CampaignDialer>>,
+    email: Option<Arc<dyn VoicemailEmailSender>>,
+    webhook: Option<WebhookTarget>,
+    events: broadcast::Sender<ScheduledCallEvent>,
+    /// Schedules with an attempt in progress
+    dialing: Mutex<HashSet<Uuid>>,
+}
+
+impl ScheduledCallService {
+    pub fn new(repository: Arc<dyn ScheduledCallRepository>, config: ScheduledCallConfig) -> Self {
+        // Validated at startup; an invalid URL only loses the webhook
+        let webhook = config.webhook_url.as_deref().and_then(|url| {
+            WebhookTarget::parse(url)
+                .map_err(|e| warn!("Scheduled call webhook disabled: {}", e))
+                .ok()
+        });
+        let (events, _) = broadcast::channel(64);
+        Self {
+            repository,
+            config,
+            time_zones: TimeZoneConfig::default(),
+            clock: system_clock(),
+            dialer: None,
+            email: None,
+            webhook,
+            events,
+            dialing: Mutex::new(HashSet::new()),
+        }
+    }
+
+    /// Take schedules without a zone in their tenant's zone
+    pub fn with_time_zones(mut self, time_zones: TimeZoneConfig) -> Self {
+        self.time_zones = time_zones;
+        self
+    }
+
+    pub fn with_clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    /// Place the calls with `dialer`; without one nothing is called
+    pub fn with_dialer(mut self, dialer: Arc<dyn CampaignDialer>) -> Self {
+        self.dialer = Some(dialer);
+        self
+    }
+
+    /// Email failed calls to the schedule's `notify_email`
+    pub fn with_email_sender(mut self, email: Arc<dyn VoicemailEmailSender>) -> Self {
+        self.email = Some(email);
+        self
+    }
+
+    pub fn subscribe(&self) -> broadcast::Receiver<ScheduledCallEvent> {
+        self.events.subscribe()
+    }
+
+    pub fn config(&self) -> &ScheduledCallConfig {
+        &self.config
+    }
+
+    /// Name of the zone of a new schedule: the requested one, else the
+    /// tenant's
+    fn zone_name(&self, realm: Option<&str>, requested: Option<&str>) -> Result<String, ScheduledCallError> {
+        match requested {
+            Some(zone) => parse_time_zone(zone)
+                .map(|zone| zone.name().to_string())
+                .map_err(ScheduledCallError::Invalid),
+            None => Ok(self.time_zones.zone_for(realm, None).name().to_string()),
+        }
+    }
+
+    fn defaults(&self, request: &CreateScheduledCall) -> (CampaignContent, RetryPolicy) {
+        let content = request.content.clone().unwrap_or_else(|| CampaignContent::Announcement {
+            prompts: vec![self.config.prompt.clone()],
+        });
+        let retry = request.retry.clone().unwrap_or_else(|| self.config.retry.clone());
+        (content, retry)
+    }
+
+    /// Schedule a call for a tenant's extension
+    pub async fn create(
+        &self,
+        request: CreateScheduledCall,
+        realm: Option<&str>,
+        created_by: &str,
+    ) -> Result<ScheduledCall, ScheduledCallError> {
+        let zone = self.zone_name(realm, request.time_zone.as_deref())?;
+        let (content, retry) = self.defaults(&request);
+        let call = ScheduledCall::new(
+            request,
+            realm.map(str::to_string),
+            zone,
+            created_by,
+            content,
+            retry,
+            self.clock.now(),
+        )?;
+        self.repository
+            .create(&call)
+            .await
+            .map_err(ScheduledCallError::Repository)?;
+        info!(
+            "Scheduled call {} to {} at {} {} by {}",
+            call.id, call.target, call.local_time, call.time_zone, call.created_by
+        );
+        Ok(call)
+    }
+
+    pub async fn get(&self, id: Uuid) -> Result<ScheduledCall, ScheduledCallError> {
+        self.repository
+            .get(id)
+            .await
+            .map_err(ScheduledCallError::Repository)?
+            .ok_or(ScheduledCallError::NotFound(id))
+    }
+
+    /// Every schedule, soonest first
+    pub async fn list(&self) -> Result<Vec<ScheduledCall>, ScheduledCallError> {
+        self.repository.list().await.map_err(ScheduledCallError::Repository)
+    }
+
+    /// Schedules calling or made by a user
+    pub async fn list_for_user(&self, username: &str) -> Result<Vec<ScheduledCall>, ScheduledCallError> {
+        let mut calls = self.list().await?;
+        calls.retain(|c| c.target == username || c.created_by == username);
+        Ok(calls)
+    }
+
+    /// Change a pending schedule
+    pub async fn update(
+        &self,
+        id: Uuid,
+        request: CreateScheduledCall,
+    ) -> Result<ScheduledCall, ScheduledCallError> {
+        let mut call = self.get(id).await?;
+        let zone = match &request.time_zone {
+            Some(_) => self.zone_name(call.realm.as_deref(), request.time_zone.as_deref())?,
+            None => call.time_zone.clone(),
+        };
+        let (content, retry) = self.defaults(&request);
+        call.reschedule(request, zone, content, retry, self.clock.now())?;
+        self.save(&call).await?;
+        info!("Rescheduled call {} to {} {}", id, call.local_time, call.time_zone);
+        Ok(call)
+    }
+
+    /// Stop a pending schedule; it is kept with its history
+    pub async fn cancel(&self, id: Uuid) -> Result<ScheduledCall, ScheduledCallError> {
+        let mut call = self.get(id).await?;
+        call.cancel(self.clock.now())?;
+        self.save(&call).await?;
+        info!("Cancelled scheduled call {}", id);
+        Ok(call)
+    }
+
+    pub async fn delete(&self, id: Uuid) -> Result<(), ScheduledCallError> {
+        match self.repository.delete(id).await {
+            Ok(true) => {
+                info!("Deleted scheduled call {}", id);
+                Ok(())
+            }
+            Ok(false) => Err(ScheduledCallError::NotFound(id)),
+            Err(e) => Err(ScheduledCallError::Repository(e)),
+        }
+    }
+
+    async fn save(&self, call: &ScheduledCall) -> Result<(), ScheduledCallError> {
+        match self.repository.update(call).await {
+            Ok(true) => Ok(()),
+            Ok(false) => Err(ScheduledCallError::NotFound(call.id)),
+            Err(e) => Err(ScheduledCallError::Repository(e)),
+        }
+    }
+
+    /// Attempts in progress
+    pub fn in_progress(&self) -> usize {
+        self.dialing.lock().unwrap().len()
+    }
+
+    /// Start the calls due now and fail the ones missed; returns how many
+    /// calls were started
+    pub async fn dial_due(self: &Arc<Self>) -> usize {
+        let Some(dialer) = self.dialer.clone() else {
+            return 0;
+        };
+        let now = self.clock.now();
+        let due = match self.repository.list_due(now).await {
+            Ok(due) => due,
+            Err(e) => {
+                error!("Failed to load due scheduled calls: {}", e);
+                return 0;
+            }
+        };
+
+        let mut started = 0;
+        for mut call in due {
+            if !self.dialing.lock().unwrap().insert(call.id) {
+                continue;
+            }
+            let late = now - call.next_attempt_at;
+            if late > ChronoDuration::seconds(self.config.max_lateness_secs as i64) {
+                warn!(
+                    "Scheduled call {} to {} missed by {}s",
+                    call.id,
+                    call.target,
+                    late.num_seconds()
+                );
+                let before = call.clone();
+                call.miss(now);
+                self.finish(&before, &call, ScheduledCallStatus::Failed, false).await;
+                self.dialing.lock().unwrap().remove(&call.id);
+                continue;
+            }
+
+            started += 1;
+            let service = self.clone();
+            let dialer = dialer.clone();
+            tokio::spawn(async move {
+                let id = call.id;
+                service.run_attempt(dialer.as_ref(), call).await;
+                service.dialing.lock().unwrap().remove(&id);
+            });
+        }
+        started
+    }
+
+    async fn run_attempt(&self, dialer: &dyn CampaignDialer, call: ScheduledCall) {
+        let attempt = DialAttempt {
+            campaign_id: call.id,
+            call_id: call.next_call_id(),
+            caller_id: self.config.caller_id.clone(),
+            number: call.target.clone(),
+            trunk: None,
+            content: call.content.clone(),
+            confirm_digit: call.confirm_digit,
+            listen: Duration::from_secs(self.config.listen_secs),
+        };
+        info!(
+            "Scheduled call {} calling {} ({})",
+            call.id, call.target, attempt.call_id
+        );
+        let result = dialer.dial(&attempt).await;
+
+        // Changed or cancelled while the call was up: the change wins
+        let mut current = match self.repository.get(call.id).await {
+            Ok(Some(current)) if current.status == ScheduledCallStatus::Pending
+                && current.next_attempt_at == call.next_attempt_at => current,
+            Ok(_) => {
+                info!("Scheduled call {} changed during its attempt", call.id);
+                return;
+            }
+            Err(e) => {
+                error!("Failed to load scheduled call {}: {}", call.id, e);
+                return;
+            }
+        };
+        let before = current.clone();
+        let finished = current.finish_attempt(&result, self.clock.now());
+        info!(
+            "Scheduled call {} to {}: {}",
+            call.id,
+            call.target,
+            current.last_result.as_deref().unwrap_or_default()
+        );
+        match finished {
+            Some(outcome) => self.finish(&before, &current, outcome, true).await,
+            None => {
+                if let Err(e) = self.save(&current).await {
+                    error!("Failed to save scheduled call {}: {}", call.id, e);
+                }
+            }
+        }
+    }
+
+    /// Save a finished occurrence, publish it and report a failure;
+    /// `attempted` when `before` is the schedule before its last attempt
+    async fn finish(
+        &self,
+        before: &ScheduledCall,
+        after: &ScheduledCall,
+        outcome: ScheduledCallStatus,
+        attempted: bool,
+    ) {
+        if let Err(e) = self.save(after).await {
+            error!("Failed to save scheduled call {}: {}", after.id, e);
+        }
+        let event = ScheduledCallEvent {
+            id: after.id,
+            target: after.target.clone(),
+            realm: after.realm.clone(),
+            local_time: before.local_time,
+            time_zone: before.time_zone.clone(),
+            outcome,
+            attempts: before.attempts + u32::from(attempted),
+            result: after.last_result.clone(),
+            timestamp: self.clock.now(),
+        };
+        if outcome == ScheduledCallStatus::Failed {
+            self.notify_failure(&event, after.notify_email.as_deref()).await;
+        }
+        let _ = self.events.send(event);
+    }
+
+    async fn notify_failure(&self, event: &ScheduledCallEvent, email: Option<&str>) {
+        if let Some(webhook) = &self.webhook {
+            let timeout = Duration::from_millis(self.config.webhook_timeout_ms);
+            match tokio::time::timeout(timeout, webhook.post(event)).await {
+                Ok(Ok(())) => {}
+                Ok(Err(e)) => warn!("Scheduled call webhook failed: {}", e),
+                Err(_) => warn!("Scheduled call webhook timed out after {:?}", timeout),
+            }
+        }
+        let (Some(sender), Some(address)) = (&self.email, email) else {
+            return;
+        };
+        let subject = format!("Scheduled call to {} failed", event.target);
+        let body = format!(
+            "The call to {} scheduled for {} ({}) failed after {} attempt(s): {}.",
+            event.target,
+            event.local_time.format("%Y-%m-%d %H:%M"),
+            event.time_zone,
+            event.attempts,
+            event.result.as_deref().unwrap_or("unknown"),
+        );
+        if let Err(e) = sender.send(address, &subject, &body).await {
+            error!("Failed to email failure of scheduled call {} to {}: {}", event.id, address, e);
+        }
+    }
+}
+
+/// Start due scheduled calls every `poll_interval_secs`
+pub fn spawn_scheduled_call_runner(service: Arc<ScheduledCallService>) -> JoinHandle<()> {
+    let period = Duration::from_secs(service.config.poll_interval_secs.max(1));
+    tokio::spawn(async move {
+        let mut ticker = tokio::time::interval(period);
+        loop {
+            ticker.tick().await;
+            service.dial_due().await;
+        }
+    })
+}
+
+/// `*77<HHMM>`: wake-up call for the caller at the next HH:MM
+pub struct WakeUpCallFeature {
+    service: Arc<ScheduledCallService>,
+}
+
+impl WakeUpCallFeature {
+    pub fn new(service: Arc<ScheduledCallService>) -> Self {
+        Self { service }
+    }
+}
+
+/// `HHMM` as a time of day
+fn parse_hhmm(digits: &str) -> Option<NaiveTime> {
+    if digits.len() != 4 {
+        return None;
+    }
+    let hour = digits[..2].parse().ok()?;
+    let minute = digits[2..].parse().ok()?;
+    NaiveTime::from_hms_opt(hour, minute, 0)
+}
+
+#[async_trait]
+impl FeatureCodeHandler for WakeUpCallFeature {
+    async fn invoke(&self, call: &FeatureCall) -> FeatureOutcome {
+        let config = self.service.config();
+        let Some(time) = parse_hhmm(&call.argument) else {
+            return FeatureOutcome::Announce(config.invalid_prompt.clone());
+        };
+        let zone = self.service.time_zones.zone_for(call.realm.as_deref(), None);
+        let local_now = self.service.clock.now().with_timezone(&zone).naive_local();
+        let mut when = local_now.date().and_time(time);
+        if when <= local_now {
+            when += ChronoDuration::days(1);
+        }
+
+        let mut request = CreateScheduledCall::new(&call.caller, when);
+        request.confirm_digit = config.feature_confirm_digit;
+        match self
+            .service
+            .create(request, call.realm.as_deref(), &call.caller)
+            .await
+        {
+            Ok(_) => FeatureOutcome::Announce(config.set_prompt.clone()),
+            Err(e) => {
+                warn!("Wake-up call of {} not set: {}", call.caller, e);
+                FeatureOutcome::Announce(config.invalid_prompt.clone())
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::domain::campaign::DialResult;
+    use crate::infrastructure::persistence::MemoryScheduledCallRepository;
+    use crate::test_support::ManualClock;
+    use chrono::TimeZone;
+    use std::collections::{HashMap, VecDeque};
+
+    /// Scripted results per number; answers numbers without a script
+    #[derive(Default)]
+    struct FakeDialer {
+        scripts: Mutex<HashMap<String, VecDeque<DialResult>>>,
+        dialed: Mutex<Vec<String>>,
+    }
+
+    impl FakeDialer {
+        fn script(self, number: &str, results: Vec<DialResult>) -> Self {
+            self.scripts
+                .lock()
+                .unwrap()
+                .insert(number.to_string(), results.into());
+            self
+        }
+    }
+
+    #[async_trait]
+    impl CampaignDialer for FakeDialer {
+        async fn dial(&self, attempt: &DialAttempt) -> DialResult {
+            self.dialed.lock().unwrap().push(attempt.call_id.clone());
+            self.scripts
+                .lock()
+                .unwrap()
+                .get_mut(&attempt.number)
+                .and_then(VecDeque::pop_front)
+                .unwrap_or_else(DialResult::answered)
+        }
+    }
+
+    #[derive(Default)]
+    struct RecordingEmail(Mutex<Vec<(String, String, String)>>);
+
+    #[async_trait]
+    impl VoicemailEmailSender for RecordingEmail {
+        async fn send(&self, to: &str, subject: &str, body: &str) -> Result<(), String> {
+            self.0
+                .lock()
+                .unwrap()
+                .push((to.to_string(), subject.to_string(), body.to_string()));
+            Ok(())
+        }
+    }
+
+    struct Fixture {
+        service: Arc<ScheduledCallService>,
+        clock: Arc<ManualClock>,
+        dialer: Arc<FakeDialer>,
+        email: Arc<RecordingEmail>,
+    }
+
+    /// Service at Monday 2024-03-04 12:00 UTC; hotel.example.com is in
+    /// New York
+    fn fixture(dialer: FakeDialer) -> Fixture {
+        let clock = Arc::new(ManualClock::starting_at(
+            Utc.with_ymd_and_hms(2024, 3, 4, 12, 0, 0).unwrap(),
+        ));
+        let dialer = Arc::new(dialer);
+        let email = Arc::new(RecordingEmail::default());
+        let mut time_zones = TimeZoneConfig::default();
+        time_zones
+            .tenants
+            .insert("hotel.example.com".to_string(), "America/New_York".to_string());
+        let config = ScheduledCallConfig {
+            retry: RetryPolicy {
+                max_attempts: 3,
+                retry_delay_secs: 120,
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let service = Arc::new(
+            ScheduledCallService::new(Arc::new(MemoryScheduledCallRepository::new()), config)
+                .with_time_zones(time_zones)
+                .with_clock(clock.clone())
+                .with_dialer(dialer.clone())
+                .with_email_sender(email.clone()),
+        );
+        Fixture {
+            service,
+            clock,
+            dialer,
+            email,
+        }
+    }
+
+    fn local(value: &str) -> NaiveDateTime {
+        NaiveDateTime::parse_from_str(value, "%Y-%m-%d %H:%M").unwrap()
+    }
+
+    async fn settle(service: &ScheduledCallService) {
+        for _ in 0..200 {
+            if service.in_progress() == 0 {
+                return;
+            }
+            tokio::time::sleep(Duration::from_millis(5)).await;
+        }
+        panic!("scheduled call attempts did not finish");
+    }
+
+    #[tokio::test]
+    async fn test_fires_at_local_time_of_tenant() {
+        let f = fixture(FakeDialer::default());
+        let mut events = f.service.subscribe();
+        let mut request = CreateScheduledCall::new("1001", local("2024-03-04 08:00"));
+        request.confirm_digit = Some('1');
+        let call = f
+            .service
+            .create(request, Some("hotel.example.com"), "api")
+            .await
+            .unwrap();
+        // 08:00 in New York is 13:00 UTC
+        assert_eq!(call.time_zone, "America/New_York");
+        assert_eq!(call.next_attempt_at, Utc.with_ymd_and_hms(2024, 3, 4, 13, 0, 0).unwrap());
+
+        assert_eq!(f.service.dial_due().await, 0);
+        f.clock.advance(Duration::from_secs(3600));
+        assert_eq!(f.service.dial_due().await, 1);
+        settle(&f.service).await;
+
+        // Answered without the digit, retried after the delay
+        assert_eq!(f.service.get(call.id).await.unwrap().last_result.as_deref(), Some("Not confirmed"));
+        assert_eq!(f.service.dial_due().await, 0);
+        f.dialer
+            .scripts
+            .lock()
+            .unwrap()
+            .insert("1001".to_string(), vec![DialResult::answered().with_digits("1")].into());
+        f.clock.advance(Duration::from_secs(120));
+        assert_eq!(f.service.dial_due().await, 1);
+        settle(&f.service).await;
+
+        let call = f.service.get(call.id).await.unwrap();
+        assert_eq!(call.status, ScheduledCallStatus::Confirmed);
+        let event = events.try_recv().unwrap();
+        assert_eq!(event.outcome, ScheduledCallStatus::Confirmed);
+        assert_eq!(event.attempts, 2);
+        assert!(f.email.0.lock().unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_no_answer_retries_then_notifies_failure() {
+        let f = fixture(FakeDialer::default().script("1002", vec![DialResult::rejected(480); 3]));
+        let mut events = f.service.subscribe();
+        let mut request = CreateScheduledCall::new("1002", local("2024-03-04 12:30"));
+        request.time_zone = Some("UTC".to_string());
+        request.notify_email = Some("frontdesk@hotel.example.com".to_string());
+        let call = f.service.create(request, None, "api").await.unwrap();
+
+        f.clock.advance(Duration::from_secs(1800));
+        for attempt in 1..=3 {
+            assert_eq!(f.service.dial_due().await, 1, "attempt {}", attempt);
+            settle(&f.service).await;
+            f.clock.advance(Duration::from_secs(120));
+        }
+        assert_eq!(f.service.dial_due().await, 0);
+        assert_eq!(f.dialer.dialed.lock().unwrap().len(), 3);
+        assert_eq!(
+            *f.dialer.dialed.lock().unwrap().last().unwrap(),
+            format!("scheduled-{}-3", call.id)
+        );
+
+        let call = f.service.get(call.id).await.unwrap();
+        assert_eq!(call.status, ScheduledCallStatus::Failed);
+        assert_eq!(call.last_result.as_deref(), Some("No answer"));
+
+        let event = events.try_recv().unwrap();
+        assert_eq!(event.outcome, ScheduledCallStatus::Failed);
+        assert_eq!(event.attempts, 3);
+        let sent = f.email.0.lock().unwrap();
+        assert_eq!(sent.len(), 1);
+        assert_eq!(sent[0].0, "frontdesk@hotel.example.com");
+        assert!(sent[0].2.contains("after 3 attempt(s): No answer"));
+    }
+
+    #[tokio::test]
+    async fn test_missed_call_is_failed_without_dialing() {
+        let f = fixture(FakeDialer::default());
+        let mut request = CreateScheduledCall::new("1003", local("2024-03-04 12:30"));
+        request.time_zone = Some("UTC".to_string());
+        request.notify_email = Some("frontdesk@hotel.example.com".to_string());
+        let call = f.service.create(request, None, "api").await.unwrap();
+
+        // Down for two hours
+        f.clock.advance(Duration::from_secs(2 * 3600));
+        assert_eq!(f.service.dial_due().await, 0);
+        assert!(f.dialer.dialed.lock().unwrap().is_empty());
+        let call = f.service.get(call.id).await.unwrap();
+        assert_eq!(call.status, ScheduledCallStatus::Failed);
+        assert_eq!(call.last_result.as_deref(), Some("Missed"));
+        assert_eq!(f.email.0.lock().unwrap().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_feature_code_sets_wake_up_call() {
+        let f = fixture(FakeDialer::default());
+        let feature = WakeUpCallFeature::new(f.service.clone());
+        let call = |argument: &str| FeatureCall {
+            call_id: "call-1".to_string(),
+            caller: "1004".to_string(),
+            realm: Some("hotel.example.com".to_string()),
+            domain: "hotel.example.com".to_string(),
+            dialed: format!("*77{}", argument),
+            argument: argument.to_string(),
+        };
+
+        // It is 07:00 in New York: 23:00 is later today, 06:30 tomorrow
+        assert_eq!(
+            feature.invoke(&call("2300")).await,
+            FeatureOutcome::Announce("wakeup-call-set".to_string())
+        );
+        feature.invoke(&call("0630")).await;
+        assert_eq!(
+            feature.invoke(&call("2460")).await,
+            FeatureOutcome::Announce("wakeup-call-invalid".to_string())
+        );
+
+        let calls = f.service.list_for_user("1004").await.unwrap();
+        let times: Vec<NaiveDateTime> = calls.iter().map(|c| c.local_time).collect();
+        assert_eq!(times, vec![local("2024-03-04 23:00"), local("2024-03-05 06:30")]);
+        assert!(calls.iter().all(|c| c.confirm_digit == Some('1') && c.created_by == "1004"));
+    }
+}
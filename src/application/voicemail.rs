@@ -0,0 +1,335 @@
+//! Voicemail retrieval
+//!
+//! Plays a mailbox's messages to its owner, each preceded by its envelope
+//! (when it was received and the caller's number), and runs the action the
+//! owner picks afterwards: call the sender back, forward the message to
+//! another mailbox with an optional recorded comment, save it to the old
+//! messages or delete it. Every change goes through the voicemail
+//! repository, so an MWI-notifying repository keeps the lamps in step.
+
+use crate::domain::audio::Language;
+use crate::domain::queue_callback::caller_number;
+use crate::domain::voicemail::{VoicemailRepository, VoicemailStatus};
+use crate::domain::voicemail_ivr::{
+    envelope_prompts, VoicemailIvrSession, VoicemailIvrState, VoicemailMessageAction,
+    VoicemailPrompt,
+};
+use crate::domain::voicemail_service::{VoicemailRecorder, VoicemailService};
+use async_trait::async_trait;
+use std::sync::Arc;
+use tracing::{info, warn};
+
+/// Longest comment recorded before a forwarded message, in seconds
+pub const MAX_COMMENT_SECS: u32 = 60;
+
+/// Calls voicemail senders back
+#[async_trait]
+pub trait VoicemailCallbackDialer: Send + Sync {
+    /// Call `number` on behalf of `owner_uri` and connect the two; returns
+    /// the Call-ID once answered
+    async fn call_back(&self, owner_uri: &str, number: &str) -> Result<String, String>;
+}
+
+/// Audio to play to the mailbox owner
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RetrievalAudio {
+    /// Prompt audio file ID
+    Prompt(String),
+    /// Recording of a message, relative to the voicemail directory
+    Message(String),
+}
+
+impl From<VoicemailPrompt> for RetrievalAudio {
+    fn from(prompt: VoicemailPrompt) -> Self {
+        Self::Prompt(prompt.audio_id().to_string())
+    }
+}
+
+/// A mailbox owner listening to their messages
+pub struct VoicemailRetrieval {
+    session: VoicemailIvrSession,
+    mailbox_id: String,
+    owner_uri: String,
+    language: Language,
+    repository: Arc<dyn VoicemailRepository>,
+    service: Arc<VoicemailService>,
+    dialer: Option<Arc<dyn VoicemailCallbackDialer>>,
+    /// Comment being recorded for a forward
+    comment: Option<VoicemailRecorder>,
+}
+
+impl VoicemailRetrieval {
+    pub fn new(
+        mailbox_id: &str,
+        owner_uri: &str,
+        repository: Arc<dyn VoicemailRepository>,
+        service: Arc<VoicemailService>,
+    ) -> Self {
+        let mut session = VoicemailIvrSession::new();
+        session.set_mailbox(mailbox_id.to_string());
+        Self {
+            session,
+            mailbox_id: mailbox_id.to_string(),
+            owner_uri: owner_uri.to_string(),
+            language: Language::En,
+            repository,
+            service,
+            dialer: None,
+            comment: None,
+        }
+    }
+
+    /// Call senders back with `dialer`
+    pub fn with_dialer(mut self, dialer: Arc<dyn VoicemailCallbackDialer>) -> Self {
+        self.dialer = Some(dialer);
+        self
+    }
+
+    /// Read envelopes in `language`
+    pub fn with_language(mut self, language: Language) -> Self {
+        self.language = language;
+        self
+    }
+
+    /// IVR session state
+    pub fn state(&self) -> &VoicemailIvrState {
+        &self.session.state
+    }
+
+    /// Check if the owner is done (hung up, exited or connected to a sender)
+    pub fn is_finished(&self) -> bool {
+        self.session.is_finished()
+    }
+
+    /// Load the mailbox and play the first message
+    pub async fn start(&mut self) -> Result<Vec<RetrievalAudio>, String> {
+        let messages = self
+            .repository
+            .list_messages(&self.mailbox_id, None)
+            .await?
+            .into_iter()
+            .filter(|m| m.status != VoicemailStatus::Deleted)
+            .collect();
+        self.session.load_messages(messages);
+        Ok(self.play_current().await)
+    }
+
+    /// Handle a digit pressed by the owner; returns what to play next
+    pub async fn press(&mut self, digit: char) -> Vec<RetrievalAudio> {
+        match self.session.state {
+            VoicemailIvrState::MessageOptions => self.run_action(digit).await,
+            VoicemailIvrState::EnteringForwardMailbox => match digit {
+                '#' => self.forward_mailbox_entered().await,
+                '*' => {
+                    self.session.finish_forward();
+                    vec![VoicemailPrompt::MessageActions.into()]
+                }
+                digit => {
+                    self.session.add_forward_digit(digit);
+                    Vec::new()
+                }
+            },
+            VoicemailIvrState::RecordingForwardComment if digit == '#' => self.forward().await,
+            _ => Vec::new(),
+        }
+    }
+
+    /// Audio heard while recording a forward comment
+    pub fn add_audio(&mut self, samples: &[i16]) {
+        if let Some(comment) = &mut self.comment {
+            if let Err(e) = comment.add_samples(samples) {
+                warn!("Comment in mailbox {} not recorded: {}", self.mailbox_id, e);
+            }
+        }
+    }
+
+    /// Envelope and recording of the current message, then the options;
+    /// a new message becomes read
+    async fn play_current(&mut self) -> Vec<RetrievalAudio> {
+        let message = match self.session.current_message() {
+            Some(message) => message.clone(),
+            None => {
+                self.session.finish();
+                return vec![VoicemailPrompt::NoMessages.into(), VoicemailPrompt::Goodbye.into()];
+            }
+        };
+        if message.is_new() {
+            match self
+                .repository
+                .update_message_status(message.id, VoicemailStatus::Read)
+                .await
+            {
+                Ok(()) => self.session.mark_current_read(),
+                Err(e) => warn!("Failed to mark message {} read: {}", message.id, e),
+            }
+        }
+
+        let mut audio: Vec<RetrievalAudio> = envelope_prompts(&message, self.language)
+            .into_iter()
+            .map(RetrievalAudio::Prompt)
+            .collect();
+        audio.push(RetrievalAudio::Message(message.audio_file_path.clone()));
+        audio.push(VoicemailPrompt::MessageActions.into());
+        self.session.offer_message_options();
+        audio
+    }
+
+    /// Move on to the next message, or say there is none
+    async fn play_next(&mut self, mut audio: Vec<RetrievalAudio>) -> Vec<RetrievalAudio> {
+        if self.session.next_message() {
+            audio.extend(self.play_current().await);
+        } else {
+            audio.extend([
+                VoicemailPrompt::NoMoreMessages.into(),
+                VoicemailPrompt::MessageActions.into(),
+            ]);
+        }
+        audio
+    }
+
+    async fn run_action(&mut self, digit: char) -> Vec<RetrievalAudio> {
+        let Some(message) = self.session.current_message().cloned() else {
+            return Vec::new();
+        };
+        match VoicemailMessageAction::from_digit(digit) {
+            Some(VoicemailMessageAction::Next) => self.play_next(Vec::new()).await,
+            Some(VoicemailMessageAction::Replay) => self.play_current().await,
+            Some(VoicemailMessageAction::Delete) => {
+                if let Err(e) = self
+                    .service
+                    .delete_message(self.repository.as_ref(), &message)
+                    .await
+                {
+                    warn!("Failed to delete message {}: {}", message.id, e);
+                    return vec![VoicemailPrompt::MessageActions.into()];
+                }
+                self.session.remove_current_message();
+                let mut audio = vec![VoicemailPrompt::MessageDeleted.into()];
+                audio.extend(self.play_current().await);
+                audio
+            }
+            Some(VoicemailMessageAction::SaveOld) => {
+                if let Err(e) = self.save(message.id).await {
+                    warn!("Failed to save message {}: {}", message.id, e);
+                    return vec![VoicemailPrompt::MessageActions.into()];
+                }
+                self.play_next(vec![VoicemailPrompt::MessageSaved.into()]).await
+            }
+            Some(VoicemailMessageAction::CallBack) => {
+                let (Some(dialer), Some(number)) = (&self.dialer, caller_number(&message.caller))
+                else {
+                    return vec![
+                        VoicemailPrompt::CallbackUnavailable.into(),
+                        VoicemailPrompt::MessageActions.into(),
+                    ];
+                };
+                match dialer.call_back(&self.owner_uri, &number).await {
+                    Ok(call_id) => {
+                        info!(
+                            "Mailbox {} called back {} for message {} (call {})",
+                            self.mailbox_id, number, message.id, call_id
+                        );
+                        // The message was dealt with
+                        if let Err(e) = self.save(message.id).await {
+                            warn!("Failed to save message {}: {}", message.id, e);
+                        }
+                        self.session.finish();
+                        vec![VoicemailPrompt::CallingBack.into()]
+                    }
+                    Err(e) => {
+                        warn!("Callback from mailbox {} to {} failed: {}", self.mailbox_id, number, e);
+                        vec![
+                            VoicemailPrompt::CallbackUnavailable.into(),
+                            VoicemailPrompt::MessageActions.into(),
+                        ]
+                    }
+                }
+            }
+            Some(VoicemailMessageAction::Forward) => {
+                self.session.start_forward();
+                vec![VoicemailPrompt::EnterForwardMailbox.into()]
+            }
+            Some(VoicemailMessageAction::Exit) => {
+                self.session.finish();
+                vec![VoicemailPrompt::Goodbye.into()]
+            }
+            None => vec![VoicemailPrompt::MessageActions.into()],
+        }
+    }
+
+    async fn save(&mut self, id: uuid::Uuid) -> Result<(), String> {
+        self.repository
+            .update_message_status(id, VoicemailStatus::Saved)
+            .await?;
+        self.session.mark_current_saved();
+        Ok(())
+    }
+
+    /// Accept the mailbox entered for a forward and start the comment
+    async fn forward_mailbox_entered(&mut self) -> Vec<RetrievalAudio> {
+        let target = self.session.forward_mailbox().to_string();
+        let exists = match self.repository.get_mailbox(&target).await {
+            Ok(mailbox) => mailbox.is_some(),
+            Err(e) => {
+                warn!("Failed to get mailbox {}: {}", target, e);
+                false
+            }
+        };
+        if !exists || target == self.mailbox_id {
+            self.session.finish_forward();
+            return vec![
+                VoicemailPrompt::ForwardUnavailable.into(),
+                VoicemailPrompt::MessageActions.into(),
+            ];
+        }
+
+        let mut comment = VoicemailRecorder::new(MAX_COMMENT_SECS);
+        comment.start();
+        self.comment = Some(comment);
+        self.session.record_forward_comment();
+        vec![VoicemailPrompt::RecordForwardComment.into()]
+    }
+
+    /// Forward the current message with the comment recorded, if any
+    async fn forward(&mut self) -> Vec<RetrievalAudio> {
+        let target = self.session.forward_mailbox().to_string();
+        let mut comment = self.comment.take();
+        if let Some(comment) = &mut comment {
+            comment.stop();
+        }
+        self.session.finish_forward();
+        let Some(message) = self.session.current_message().cloned() else {
+            return Vec::new();
+        };
+
+        let forwarded = match self.service.save_forward(&message, &target, comment.as_ref()) {
+            Ok(forwarded) => forwarded,
+            Err(e) => {
+                warn!("Failed to forward message {} to {}: {}", message.id, target, e);
+                return vec![
+                    VoicemailPrompt::ForwardUnavailable.into(),
+                    VoicemailPrompt::MessageActions.into(),
+                ];
+            }
+        };
+        match self.repository.create_message(forwarded).await {
+            Ok(forwarded) => {
+                info!(
+                    "Message {} forwarded from mailbox {} to {} as {}",
+                    message.id, self.mailbox_id, target, forwarded.id
+                );
+                vec![
+                    VoicemailPrompt::MessageForwarded.into(),
+                    VoicemailPrompt::MessageActions.into(),
+                ]
+            }
+            Err(e) => {
+                warn!("Failed to forward message {} to {}: {}", message.id, target, e);
+                vec![
+                    VoicemailPrompt::ForwardUnavailable.into(),
+                    VoicemailPrompt::MessageActions.into(),
+                ]
+            }
+        }
+    }
+}
@@ -1,30 +1,644 @@
 //! Configuration management
 
+use crate::application::chat::ChatConfig;
+use crate::application::scheduled_call::ScheduledCallConfig;
+use crate::domain::billing::AnswerSupervisionConfig;
+use crate::domain::call_screening::ScreeningPolicy;
+use crate::domain::call_survey::SurveyPolicy;
+use crate::domain::cdr_retention::CdrRetentionConfig;
+use crate::domain::class_of_service::ClassOfServiceConfig;
+use crate::domain::device_enrollment::EnrollmentConfig;
+use crate::domain::device_provisioning::ProvisioningConfig;
+use crate::domain::device_token::DeviceTokenConfig;
+use crate::domain::feature_code::FeatureCodeConfig;
+use crate::domain::fraud_detection::FraudRules;
+use crate::domain::privacy::PrivacyConfig;
+use crate::domain::shared::{NumberingPlan, TimeZoneConfig};
+use crate::domain::tenant_branding::TenantBranding;
+use crate::infrastructure::call_debug::CallDebugConfig;
+use crate::infrastructure::idempotency::IdempotencyConfig;
+use crate::infrastructure::lnp::LnpConfig;
+use crate::infrastructure::sequence_vault::SequenceVaultConfig;
+use crate::infrastructure::telemetry::TelemetryConfig;
+use crate::infrastructure::media::port_allocator::{DEFAULT_RTP_PORT_MAX, DEFAULT_RTP_PORT_MIN};
+use crate::infrastructure::media::{BandwidthConfig, CapacityConfig, MohClassesConfig, RingbackConfig};
+use crate::infrastructure::protocols::sip::{
+    CodecFallbackPolicy, ExternalAddressConfig, HeaderRulesConfig, HoldPolicy, InfoPolicy, InternalServicesConfig,
+    MessagePolicy, OutboundRegistrationPolicy, OverloadConfig, QuirkRule, RedirectPolicy, SipResolverConfig,
+    SipTimerConfig, SrtpRekeyPolicy, SurvivabilityConfig, TakeoverPolicy, TransferPolicy,
+    TrunkChannelConfig, WarmTransferConfig,
+};
+use crate::infrastructure::replication::ReplicationConfig;
+use crate::infrastructure::secrets::SecretsConfig;
+use crate::infrastructure::setup_latency::SetupLatencyConfig;
+use crate::infrastructure::storage::StorageConfig;
+use crate::infrastructure::transcription::TranscriptionConfig;
 use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::path::Path;
+
+pub mod preflight;
+pub mod redact;
+
+pub use preflight::{Preflight, PreflightCode, PreflightFinding, PreflightReport, Severity};
+pub use redact::Redact;
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Config {
     pub server: ServerConfig,
     pub sip: SipConfig,
     pub database: DatabaseConfig,
+    #[serde(default)]
+    pub audio: AudioConfig,
+    #[serde(default)]
+    pub media: MediaConfig,
+    #[serde(default)]
+    pub fraud: FraudConfig,
+    /// Destination categories users may call
+    #[serde(default)]
+    pub class_of_service: ClassOfServiceConfig,
+    /// Per-device tokens phones use for the directory
+    #[serde(default)]
+    pub devices: Vec<DeviceTokenConfig>,
+    /// Provisioned phones and their configuration resync
+    #[serde(default)]
+    pub provisioning: ProvisioningConfig,
+    /// Softphone enrollment links and the connection details they hand out
+    #[serde(default)]
+    pub enrollment: EnrollmentConfig,
+    /// Wake-up calls and reminders placed at a local time
+    #[serde(default)]
+    pub scheduled_calls: ScheduledCallConfig,
+    /// Hot standby replication to a peer node
+    #[serde(default)]
+    pub replication: ReplicationConfig,
+    /// Voicemail speech-to-text
+    #[serde(default)]
+    pub transcription: TranscriptionConfig,
+    /// Per-tenant MOH, prompts and outbound caller identity
+    #[serde(default)]
+    pub branding: BrandingConfig,
+    /// Pre-answer screening of calls to users who enable it
+    #[serde(default)]
+    pub screening: ScreeningPolicy,
+    /// Post-call surveys of queue callers
+    #[serde(default)]
+    pub surveys: SurveyPolicy,
+    /// CDR retention, archiving and partitioning
+    #[serde(default)]
+    pub cdr_retention: CdrRetentionConfig,
+    /// Debug logging of single calls, switched on at runtime
+    #[serde(default)]
+    pub call_debug: CallDebugConfig,
+    /// Country rules for reading phone numbers written in any format
+    #[serde(default)]
+    pub numbering: NumberingPlan,
+    /// Internal chat and presence over the WebSocket
+    #[serde(default)]
+    pub chat: ChatConfig,
+    /// Default time zones of API responses and date filters
+    #[serde(default)]
+    pub time_zones: TimeZoneConfig,
+    /// Star codes of PBX features, per tenant
+    #[serde(default)]
+    pub feature_codes: FeatureCodeConfig,
+    /// OpenTelemetry trace export
+    #[serde(default)]
+    pub telemetry: TelemetryConfig,
+    /// Disk quotas and free-space floor of recordings, voicemail, captures
+    /// and archives
+    #[serde(default)]
+    pub storage: StorageConfig,
+    /// Latency SLOs of call setup and where breaches are reported
+    #[serde(default)]
+    pub setup_latency: SetupLatencyConfig,
+    /// Number portability lookups of outbound calls
+    #[serde(default)]
+    pub lnp: LnpConfig,
+    /// Which event trunk calls are billed from and when answers are
+    /// suspect
+    #[serde(default)]
+    pub answer_supervision: AnswerSupervisionConfig,
+    /// Persistence of dialog CSeqs and RTP SSRCs across restarts
+    #[serde(default)]
+    pub sequences: SequenceVaultConfig,
+    /// Pseudonym key and retention minimum of erasure requests
+    #[serde(default)]
+    pub privacy: PrivacyConfig,
+    /// Keys stored credentials are encrypted with
+    #[serde(default)]
+    pub secrets: SecretsConfig,
+}
+
+/// Prefix of environment variables overriding settings, e.g.
+/// `YAKYAK_SIP__BIND_PORT` for `sip.bind_port`; sections are separated by
+/// `__`
+pub const ENV_PREFIX: &str = "YAKYAK_";
+
+/// Separator of the section and key names of an environment variable
+const ENV_SEPARATOR: &str = "__";
+
+impl Config {
+    /// Read a TOML configuration file; settings it leaves out are the
+    /// defaults
+    pub fn from_file(path: &Path) -> Result<Self, String> {
+        Self::load_from(Some(path), std::iter::empty())
+    }
+
+    /// Load the configuration: the defaults, then the TOML or YAML file
+    /// (by its extension), then `YAKYAK_` environment variables
+    pub fn load(path: Option<&Path>) -> Result<Self, String> {
+        Self::load_from(path, std::env::vars())
+    }
+
+    fn load_from(
+        path: Option<&Path>,
+        vars: impl IntoIterator<Item = (String, String)>,
+    ) -> Result<Self, String> {
+        let mut settings = toml::Value::try_from(Config::default())
+            .map_err(|e| format!("Cannot represent the default configuration: {}", e))?;
+        if let Some(path) = path {
+            merge(&mut settings, read_settings(path)?);
+        }
+        let mut overrides = Vec::new();
+        for (name, value) in vars {
+            // Variables without a section, like `YAKYAK_SECRET_KEY`, are not
+            // settings
+            let key = name.strip_prefix(ENV_PREFIX).filter(|key| key.contains(ENV_SEPARATOR));
+            let Some(key) = key else {
+                continue;
+            };
+            let mut typed = settings.clone();
+            let path = apply_override(&mut typed, &name, key, &value, false)?;
+            // An unset optional setting has no value to take the type from:
+            // keep the value a string when only that makes a valid
+            // configuration, like `1234` for a string setting
+            if typed.clone().try_into::<Config>().is_err() {
+                let mut text = settings.clone();
+                apply_override(&mut text, &name, key, &value, true)?;
+                if text.clone().try_into::<Config>().is_ok() {
+                    typed = text;
+                }
+            }
+            settings = typed;
+            overrides.push((name, path));
+        }
+
+        let config = settings.try_into::<Config>().map_err(|e| {
+            let message = e.to_string();
+            format!("Invalid configuration: {}", message.trim_end().replace('\n', " "))
+        })?;
+
+        // Whatever no setting took was dropped while loading: refuse it
+        // rather than run without the override
+        let known = toml::Value::try_from(&config)
+            .map_err(|e| format!("Cannot represent the configuration: {}", e))?;
+        for (name, path) in overrides {
+            let found = path
+                .iter()
+                .try_fold(&known, |value, key| value.as_table().and_then(|table| table.get(key)));
+            if found.is_none() {
+                return Err(format!("{}: {} is not a setting", name, path.join(".")));
+            }
+        }
+        Ok(config)
+    }
+
+    /// Refuse settings the server cannot start with, naming the first one
+    pub fn validate(&self) -> Result<(), String> {
+        match self.problems().into_iter().next() {
+            Some((_, message)) => Err(message),
+            None => Ok(()),
+        }
+    }
+
+    /// Every setting the server cannot start with, and why
+    pub fn problems(&self) -> Vec<(&'static str, String)> {
+        let mut problems = Vec::new();
+        if self.server.host.trim().is_empty() {
+            problems.push(("server.host", "server.host must not be empty".to_string()));
+        }
+        if self.server.port == 0 {
+            problems.push(("server.port", "server.port must not be 0".to_string()));
+        }
+        if self.sip.bind_port == 0 {
+            problems.push(("sip.bind_port", "sip.bind_port must not be 0".to_string()));
+        }
+        if self.sip.domain.trim().is_empty() {
+            problems.push(("sip.domain", "sip.domain must not be empty".to_string()));
+        }
+        if self.sip.rtp_port_min == 0 {
+            problems.push(("sip.rtp_port_min", "sip.rtp_port_min must not be 0".to_string()));
+        }
+        if self.sip.rtp_port_min > self.sip.rtp_port_max {
+            problems.push((
+                "sip.rtp_port_min",
+                format!(
+                    "sip.rtp_port_min {} is above sip.rtp_port_max {}",
+                    self.sip.rtp_port_min, self.sip.rtp_port_max
+                ),
+            ));
+        } else if !has_rtp_pair(self.sip.rtp_port_min, self.sip.rtp_port_max) {
+            problems.push((
+                "sip.rtp_port_min",
+                format!(
+                    "sip.rtp_port_min {} to sip.rtp_port_max {} holds no RTP/RTCP port pair",
+                    self.sip.rtp_port_min, self.sip.rtp_port_max
+                ),
+            ));
+        }
+        if self.sip.tcp_idle_timeout_secs == 0 {
+            problems.push((
+                "sip.tcp_idle_timeout_secs",
+                "sip.tcp_idle_timeout_secs must not be 0".to_string(),
+            ));
+        }
+        if self.sip.tcp_max_connections == 0 {
+            problems.push((
+                "sip.tcp_max_connections",
+                "sip.tcp_max_connections must not be 0".to_string(),
+            ));
+        }
+        if self.database.url.trim().is_empty() {
+            problems.push(("database.url", "database.url must not be empty".to_string()));
+        }
+        problems
+    }
+}
+
+/// Whether `min..=max` holds an even RTP port and the RTCP port above it
+fn has_rtp_pair(min: u16, max: u16) -> bool {
+    match min.checked_add(min % 2) {
+        Some(rtp) => rtp < max,
+        None => false,
+    }
+}
+
+/// Settings of a configuration file, YAML for `.yaml` and `.yml` files
+fn read_settings(path: &Path) -> Result<toml::Value, String> {
+    let text = std::fs::read_to_string(path)
+        .map_err(|e| format!("Cannot read {}: {}", path.display(), e))?;
+    let yaml = matches!(
+        path.extension().and_then(|extension| extension.to_str()),
+        Some("yaml" | "yml")
+    );
+    if yaml {
+        ::config::Config::builder()
+            .add_source(::config::File::from_str(&text, ::config::FileFormat::Yaml))
+            .build()
+            .and_then(|settings| settings.try_deserialize())
+            .map_err(|e| format!("Invalid configuration {}: {}", path.display(), e))
+    } else {
+        text.parse::<toml::Table>()
+            .map(toml::Value::Table)
+            .map_err(|e| format!("Invalid configuration {}: {}", path.display(), e))
+    }
+}
+
+/// Lay `overlay` over `base`, table by table
+fn merge(base: &mut toml::Value, overlay: toml::Value) {
+    match (base, overlay) {
+        (toml::Value::Table(base), toml::Value::Table(overlay)) => {
+            for (key, value) in overlay {
+                match base.get_mut(&key) {
+                    Some(existing) => merge(existing, value),
+                    None => {
+                        base.insert(key, value);
+                    }
+                }
+            }
+        }
+        (base, overlay) => *base = overlay,
+    }
+}
+
+/// Set the setting named by an environment variable and return its path; the
+/// value is read as TOML (`5060`, `true`, `["a", "b"]`) unless the setting is
+/// a string or `as_string` is set
+fn apply_override(
+    settings: &mut toml::Value,
+    name: &str,
+    key: &str,
+    raw: &str,
+    as_string: bool,
+) -> Result<Vec<String>, String> {
+    let path: Vec<String> = key.split(ENV_SEPARATOR).map(str::to_lowercase).collect();
+    if path.iter().any(String::is_empty) {
+        return Err(format!("{}: not a setting name", name));
+    }
+
+    let mut table = settings;
+    for section in &path[..path.len() - 1] {
+        let toml::Value::Table(sections) = table else {
+            return Err(format!("{}: {} is not a section", name, path.join(".")));
+        };
+        table = sections
+            .entry(section.clone())
+            .or_insert_with(|| toml::Value::Table(toml::Table::new()));
+    }
+    let toml::Value::Table(values) = table else {
+        return Err(format!("{}: {} is not a section", name, path.join(".")));
+    };
+
+    let last = &path[path.len() - 1];
+    let value = match values.get(last) {
+        _ if as_string => toml::Value::String(raw.to_string()),
+        Some(toml::Value::String(_)) => toml::Value::String(raw.to_string()),
+        _ => format!("value = {}", raw)
+            .parse::<toml::Table>()
+            .ok()
+            .and_then(|mut parsed| parsed.remove("value"))
+            .unwrap_or_else(|| toml::Value::String(raw.to_string())),
+    };
+    values.insert(last.clone(), value);
+    Ok(path)
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ServerConfig {
     pub host: String,
     pub port: u16,
+    /// Page sizes of the API's list endpoints
+    #[serde(default)]
+    pub pagination: PaginationConfig,
+    /// Limits on call control commands sent over the WebSocket
+    #[serde(default)]
+    pub call_control: CallControlConfig,
+    /// Serve Swagger UI for the OpenAPI document at `/api/admin/docs`
+    #[serde(default)]
+    pub swagger_ui: bool,
+    /// Replay of retried requests sent with an `Idempotency-Key`
+    #[serde(default)]
+    pub idempotency: IdempotencyConfig,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct PaginationConfig {
+    /// Page size when a request has no `limit`
+    pub default_limit: i64,
+    /// Largest `limit` a request may ask for
+    pub max_limit: i64,
+}
+
+impl Default for PaginationConfig {
+    fn default() -> Self {
+        Self {
+            default_limit: 50,
+            max_limit: 500,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct CallControlConfig {
+    /// Commands a connection may send per `window_secs`
+    pub max_commands: usize,
+    pub window_secs: u64,
+}
+
+impl Default for CallControlConfig {
+    fn default() -> Self {
+        Self {
+            max_commands: 20,
+            window_secs: 10,
+        }
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct SipConfig {
     pub bind_address: String,
     pub bind_port: u16,
+    /// Separate IPv6 listen address (e.g. "::"); `bind_address` may also
+    /// be "::" for a single dual-stack socket
+    #[serde(default)]
+    pub bind_address_v6: Option<String>,
     pub domain: String,
+    /// First port of the RTP range; RTP takes even ports, RTCP the odd one
+    /// above
+    #[serde(default = "default_rtp_port_min")]
+    pub rtp_port_min: u16,
+    /// Last port of the RTP range
+    #[serde(default = "default_rtp_port_max")]
+    pub rtp_port_max: u16,
+    /// Close TCP connections idle for this many seconds
+    #[serde(default = "default_tcp_idle_timeout_secs")]
+    pub tcp_idle_timeout_secs: u64,
+    /// Maximum number of open TCP connections
+    #[serde(default = "default_tcp_max_connections")]
+    pub tcp_max_connections: usize,
+    /// Limits on following 3xx redirects of forwarded calls
+    #[serde(default)]
+    pub redirect: RedirectPolicy,
+    /// Per-device interop workarounds, matched on User-Agent in order
+    #[serde(default = "QuirkRule::defaults")]
+    pub quirks: Vec<QuirkRule>,
+    /// Timeout and recovery destination of blind transfers
+    #[serde(default)]
+    pub transfer: TransferPolicy,
+    /// Who may take over whose calls with INVITE/Replaces (shared line,
+    /// attendant pickup)
+    #[serde(default)]
+    pub takeover: TakeoverPolicy,
+    /// Echo, milliwatt and readback test services for installers
+    #[serde(default)]
+    pub internal_services: InternalServicesConfig,
+    /// Headers added, removed and passed through per trunk and route
+    #[serde(default)]
+    pub header_rules: HeaderRulesConfig,
+    /// Address advertised in SDP, Contact and Via when behind NAT
+    #[serde(default)]
+    pub external_address: ExternalAddressConfig,
+    /// Handling of INFO requests that do not carry DTMF
+    #[serde(default)]
+    pub info: InfoPolicy,
+    /// Instant message store-and-forward and delivery receipts
+    #[serde(default)]
+    pub message: MessagePolicy,
+    /// Hold reminders and recovery of calls held too long
+    #[serde(default)]
+    pub hold: HoldPolicy,
+    /// Re-keying of SRTP media on long calls
+    #[serde(default)]
+    pub srtp_rekey: SrtpRekeyPolicy,
+    /// Fallback from Opus to G.711 when a leg's media cannot be decoded
+    #[serde(default)]
+    pub codec_fallback: CodecFallbackPolicy,
+    /// DTMF codes and timeouts of consultative transfers
+    #[serde(default)]
+    pub warm_transfer: WarmTransferConfig,
+    /// Refresh and retry of registrations to upstream providers
+    #[serde(default)]
+    pub outbound_registration: OutboundRegistrationPolicy,
+    /// NAPTR/SRV resolution and failover of trunk and external destinations
+    #[serde(default)]
+    pub dns: SipResolverConfig,
+    /// Transaction timers (T1/T2/T4, Timer B/D/F) per transport and trunk
+    #[serde(default)]
+    pub timers: SipTimerConfig,
+    /// Overload thresholds, shedding and 503 rejection of new dialogs
+    #[serde(default)]
+    pub overload: OverloadConfig,
+    /// Local registration and routing of a branch node while its upstream
+    /// is unreachable
+    #[serde(default)]
+    pub survivability: SurvivabilityConfig,
+    /// Channels per trunk, reserved per direction, and outbound failover
+    #[serde(default)]
+    pub trunk_channels: TrunkChannelConfig,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct DatabaseConfig {
     pub url: String,
+    /// Serve SIP digest auth from recently verified HA1s while the database
+    /// is unavailable (opt-in)
+    #[serde(default)]
+    pub degraded_auth_cache: bool,
+    /// How long a cached HA1 stays usable, in seconds
+    #[serde(default = "default_degraded_auth_cache_ttl_secs")]
+    pub degraded_auth_cache_ttl_secs: u64,
+    /// Maximum number of cached HA1s
+    #[serde(default = "default_degraded_auth_cache_capacity")]
+    pub degraded_auth_cache_capacity: usize,
+    /// Deliver call events at least once through the `event_outbox` table
+    /// instead of in memory only (opt-in)
+    #[serde(default)]
+    pub event_outbox: bool,
+    /// File journaling CDR writes queued while the database is unavailable,
+    /// replayed on startup; empty keeps the queue in memory only
+    #[serde(default = "default_cdr_journal_path")]
+    pub cdr_journal_path: String,
+}
+
+fn default_rtp_port_min() -> u16 {
+    DEFAULT_RTP_PORT_MIN
+}
+
+fn default_rtp_port_max() -> u16 {
+    DEFAULT_RTP_PORT_MAX
+}
+
+fn default_tcp_idle_timeout_secs() -> u64 {
+    600
+}
+
+fn default_tcp_max_connections() -> usize {
+    1024
+}
+
+fn default_degraded_auth_cache_ttl_secs() -> u64 {
+    3600
+}
+
+fn default_degraded_auth_cache_capacity() -> usize {
+    10_000
+}
+
+fn default_cdr_journal_path() -> String {
+    "data/cdr_journal.jsonl".to_string()
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AudioConfig {
+    /// Root directory for uploaded prompts, announcements and MOH
+    pub library_root: String,
+    /// Downmix/resample uploads to 8 kHz mono instead of rejecting them
+    pub auto_convert: bool,
+    /// Maximum number of decoded audio files kept in memory
+    pub pcm_cache_capacity: usize,
+}
+
+impl Default for AudioConfig {
+    fn default() -> Self {
+        Self {
+            library_root: "audio/library".to_string(),
+            auto_convert: true,
+            pcm_cache_capacity: 32,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct MediaConfig {
+    /// Shortest packet time accepted from a remote, in milliseconds
+    pub min_ptime_ms: u32,
+    /// Longest packet time accepted from a remote, in milliseconds
+    pub max_ptime_ms: u32,
+    /// What callers hear while the callee alerts: local ringback or the
+    /// callee's early media
+    pub ringback: RingbackConfig,
+    /// Load thresholds above which new calls prefer low-bandwidth codecs
+    pub capacity: CapacityConfig,
+    /// Negotiate the RTP audio level header extension with SIP phones, so
+    /// conferences can skip decoding silent participants
+    pub audio_level_extension: bool,
+    /// Bandwidth estimation and REMB feedback of WebRTC legs
+    pub bandwidth: BandwidthConfig,
+    /// Named MOH classes: playlists per queue or tenant
+    pub moh: MohClassesConfig,
+}
+
+impl Default for MediaConfig {
+    fn default() -> Self {
+        Self {
+            min_ptime_ms: 10,
+            max_ptime_ms: 60,
+            ringback: RingbackConfig::default(),
+            capacity: CapacityConfig::default(),
+            audio_level_extension: false,
+            bandwidth: BandwidthConfig::default(),
+            moh: MohClassesConfig::default(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct FraudConfig {
+    /// Check outbound calls against velocity rules
+    pub enabled: bool,
+    /// File the in-memory counters are snapshotted to
+    pub state_path: String,
+    /// Snapshot interval in seconds
+    pub persist_interval_secs: u64,
+    /// Rules for callers without a tenant override
+    pub rules: FraudRules,
+    /// Per-tenant overrides, keyed by realm
+    pub tenants: HashMap<String, FraudRules>,
+}
+
+impl Default for FraudConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            state_path: "data/fraud_state.json".to_string(),
+            persist_interval_secs: 60,
+            rules: FraudRules::default(),
+            tenants: HashMap::new(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct BrandingConfig {
+    /// MOH played to calls whose tenant has no MOH file of its own
+    pub default_moh_source: String,
+    /// Tenant branding, keyed by realm
+    pub tenants: HashMap<String, TenantBranding>,
+}
+
+impl Default for BrandingConfig {
+    fn default() -> Self {
+        Self {
+            default_moh_source: "moh/default.wav".to_string(),
+            tenants: HashMap::new(),
+        }
+    }
 }
 
 impl Default for Config {
@@ -33,15 +647,199 @@ impl Default for Config {
             server: ServerConfig {
                 host: "0.0.0.0".to_string(),
                 port: 8080,
+                pagination: PaginationConfig::default(),
+                call_control: CallControlConfig::default(),
+                swagger_ui: false,
+                idempotency: IdempotencyConfig::default(),
             },
             sip: SipConfig {
                 bind_address: "0.0.0.0".to_string(),
                 bind_port: 5060,
+                bind_address_v6: None,
                 domain: "localhost".to_string(),
+                rtp_port_min: DEFAULT_RTP_PORT_MIN,
+                rtp_port_max: DEFAULT_RTP_PORT_MAX,
+                tcp_idle_timeout_secs: default_tcp_idle_timeout_secs(),
+                tcp_max_connections: default_tcp_max_connections(),
+                redirect: RedirectPolicy::default(),
+                quirks: QuirkRule::defaults(),
+                transfer: TransferPolicy::default(),
+                takeover: TakeoverPolicy::default(),
+                internal_services: InternalServicesConfig::default(),
+                header_rules: HeaderRulesConfig::default(),
+                external_address: ExternalAddressConfig::default(),
+                info: InfoPolicy::default(),
+                message: MessagePolicy::default(),
+                hold: HoldPolicy::default(),
+                srtp_rekey: SrtpRekeyPolicy::default(),
+                codec_fallback: CodecFallbackPolicy::default(),
+                warm_transfer: WarmTransferConfig::default(),
+                outbound_registration: OutboundRegistrationPolicy::default(),
+                dns: SipResolverConfig::default(),
+                timers: SipTimerConfig::default(),
+                overload: OverloadConfig::default(),
+                survivability: SurvivabilityConfig::default(),
+                trunk_channels: TrunkChannelConfig::default(),
             },
             database: DatabaseConfig {
                 url: "postgres://postgres@localhost/yakyak".to_string(),
+                degraded_auth_cache: false,
+                degraded_auth_cache_ttl_secs: default_degraded_auth_cache_ttl_secs(),
+                degraded_auth_cache_capacity: default_degraded_auth_cache_capacity(),
+                event_outbox: false,
+                cdr_journal_path: default_cdr_journal_path(),
             },
+            audio: AudioConfig::default(),
+            media: MediaConfig::default(),
+            fraud: FraudConfig::default(),
+            class_of_service: ClassOfServiceConfig::default(),
+            devices: Vec::new(),
+            provisioning: ProvisioningConfig::default(),
+            enrollment: EnrollmentConfig::default(),
+            scheduled_calls: ScheduledCallConfig::default(),
+            replication: ReplicationConfig::default(),
+            transcription: TranscriptionConfig::default(),
+            branding: BrandingConfig::default(),
+            screening: ScreeningPolicy::default(),
+            surveys: SurveyPolicy::default(),
+            cdr_retention: CdrRetentionConfig::default(),
+            call_debug: CallDebugConfig::default(),
+            numbering: NumberingPlan::default(),
+            chat: ChatConfig::default(),
+            time_zones: TimeZoneConfig::default(),
+            feature_codes: FeatureCodeConfig::default(),
+            telemetry: TelemetryConfig::default(),
+            storage: StorageConfig::default(),
+            setup_latency: SetupLatencyConfig::default(),
+            lnp: LnpConfig::default(),
+            answer_supervision: AnswerSupervisionConfig::default(),
+            sequences: SequenceVaultConfig::default(),
+            privacy: PrivacyConfig::default(),
+            secrets: SecretsConfig::default(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use uuid::Uuid;
+
+    fn write_file(extension: &str, text: &str) -> std::path::PathBuf {
+        let path = std::env::temp_dir().join(format!("yakyak-config-{}.{}", Uuid::new_v4(), extension));
+        std::fs::write(&path, text).unwrap();
+        path
+    }
+
+    fn vars(pairs: &[(&str, &str)]) -> Vec<(String, String)> {
+        pairs
+            .iter()
+            .map(|(name, value)| (name.to_string(), value.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn test_file_then_environment_over_defaults() {
+        let path = write_file("toml", "[sip]\ndomain = \"pbx.example.com\"\n\n[server]\nport = 9000\n");
+
+        let config = Config::load_from(
+            Some(&path),
+            vars(&[
+                ("YAKYAK_SIP__BIND_PORT", "5070"),
+                ("YAKYAK_DATABASE__URL", "postgres://db.internal/yakyak"),
+                ("YAKYAK_SCHEDULED_CALLS__CALLER_ID", "5000"),
+                ("YAKYAK_SECRET_KEY", "not a setting"),
+                ("HOME", "/root"),
+            ]),
+        )
+        .unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(config.sip.domain, "pbx.example.com");
+        assert_eq!(config.sip.bind_port, 5070);
+        assert_eq!(config.server.port, 9000);
+        // Left out of the file: the defaults
+        assert_eq!(config.server.host, "0.0.0.0");
+        assert_eq!(config.sip.bind_address, "0.0.0.0");
+        assert_eq!(config.database.url, "postgres://db.internal/yakyak");
+        // Digits of a string setting stay a string
+        assert_eq!(config.scheduled_calls.caller_id, "5000");
+        assert!(config.validate().is_ok());
+    }
+
+    #[test]
+    fn test_yaml_file() {
+        let path = write_file("yaml", "sip:\n  domain: pbx.example.com\n  bind_port: 5080\n");
+
+        let config = Config::load_from(Some(&path), Vec::new()).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(config.sip.domain, "pbx.example.com");
+        assert_eq!(config.sip.bind_port, 5080);
+        assert_eq!(config.server.port, 8080);
+    }
+
+    #[test]
+    fn test_bad_value_names_the_setting() {
+        let e = Config::load_from(None, vars(&[("YAKYAK_SIP__BIND_PORT", "abc")])).unwrap_err();
+        assert!(e.contains("sip.bind_port"), "{}", e);
+
+        let e = Config::load_from(None, vars(&[("YAKYAK_SIP__DOMAIN__NAME", "x")])).unwrap_err();
+        assert!(e.contains("YAKYAK_SIP__DOMAIN__NAME"), "{}", e);
+    }
+
+    #[test]
+    fn test_unknown_setting_is_refused() {
+        let e = Config::load_from(None, vars(&[("YAKYAK_SIP__REALM", "1234")])).unwrap_err();
+        assert_eq!(e, "YAKYAK_SIP__REALM: sip.realm is not a setting");
+
+        let e = Config::load_from(None, vars(&[("YAKYAK_NOSUCH__KEY", "x")])).unwrap_err();
+        assert!(e.contains("YAKYAK_NOSUCH__KEY"), "{}", e);
+    }
+
+    #[test]
+    fn test_digits_of_unset_string_setting_stay_a_string() {
+        let config = Config::load_from(
+            None,
+            vars(&[("YAKYAK_LNP__API_KEY", "1234"), ("YAKYAK_SIP__BIND_PORT", "5070")]),
+        )
+        .unwrap();
+
+        assert_eq!(config.lnp.api_key.as_ref().map(|key| key.expose()), Some("1234"));
+        assert_eq!(config.sip.bind_port, 5070);
+    }
+
+    #[test]
+    fn test_validate_refuses_broken_setups() {
+        assert!(Config::default().validate().is_ok());
+
+        let mut config = Config::default();
+        config.sip.bind_port = 0;
+        assert_eq!(config.validate().unwrap_err(), "sip.bind_port must not be 0");
+
+        let mut config = Config::default();
+        config.sip.domain = " ".to_string();
+        assert_eq!(config.validate().unwrap_err(), "sip.domain must not be empty");
+
+        let mut config = Config::default();
+        config.sip.tcp_max_connections = 0;
+        assert_eq!(config.validate().unwrap_err(), "sip.tcp_max_connections must not be 0");
+
+        let mut config = Config::default();
+        config.sip.rtp_port_min = 20500;
+        config.sip.rtp_port_max = 20000;
+        assert!(config.validate().unwrap_err().contains("sip.rtp_port_min"));
+
+        for (min, max) in [(65535, 65535), (20001, 20001), (20001, 20002)] {
+            let mut config = Config::default();
+            config.sip.rtp_port_min = min;
+            config.sip.rtp_port_max = max;
+            assert!(config.validate().unwrap_err().contains("no RTP/RTCP port pair"), "{}-{}", min, max);
         }
+
+        let mut config = Config::default();
+        config.sip.rtp_port_min = 20001;
+        config.sip.rtp_port_max = 20003;
+        assert!(config.validate().is_ok());
     }
 }
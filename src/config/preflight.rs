@@ -0,0 +1,971 @@
+//! Startup preflight checks
+//!
+//! Before any service starts, every config section is checked for what
+//! parses but cannot work: listeners sharing a port, an IPv6 address where
+//! an IPv4 one is needed, missing files, a TLS certificate that is expired
+//! or does not belong to its key, hosts that do not resolve, and an
+//! unreachable database. All findings are collected rather than stopping
+//! at the first one. Each has a stable code; fatal findings keep the server
+//! from starting, warnings are only reported.
+//!
+//! `yakyak check-config` runs the same checks and exits without starting
+//! anything.
+
+use super::Config;
+use crate::infrastructure::protocols::sip::{AddressAdvertiser, HeaderRulesEngine, QuirksRegistry};
+use crate::infrastructure::replication::{crypto_provider, ReplicationTlsConfig};
+use chrono::{DateTime, Utc};
+use rustls::pki_types::{CertificateDer, PrivateKeyDer};
+use rustls::ServerConfig;
+use rustls_pemfile::{certs, private_key};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::fs::File;
+use std::io::BufReader;
+use std::net::{IpAddr, SocketAddr};
+use std::path::Path;
+use std::time::Duration;
+
+/// Certificates expiring within this many days get a warning
+pub const CERTIFICATE_EXPIRY_WARNING_DAYS: i64 = 30;
+
+/// How long a host name lookup or the database connection may take
+const CHECK_TIMEOUT: Duration = Duration::from_secs(3);
+
+/// Default port of trunk hosts given without one
+const DEFAULT_SIP_PORT: u16 = 5060;
+
+/// Whether a finding keeps the server from starting
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Severity {
+    Warning,
+    Fatal,
+}
+
+/// Stable code of a finding
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PreflightCode {
+    /// An address does not parse
+    InvalidAddress,
+    /// An address of the wrong family (IPv4/IPv6) for its setting
+    AddressFamilyMismatch,
+    /// Two listeners, or a listener and the RTP port range, share a port
+    PortConflict,
+    /// A value out of range or inconsistent with another one
+    InvalidValue,
+    /// Quirk, header or address rules that do not compile
+    InvalidRules,
+    /// The database URL is not a PostgreSQL URL
+    DatabaseUrlInvalid,
+    /// The database cannot be connected to
+    DatabaseUnreachable,
+    /// TLS is required but not configured
+    TlsSettingsMissing,
+    /// A certificate, key or CA file cannot be read or parsed
+    TlsFileUnreadable,
+    /// The certificate does not belong to the private key
+    TlsKeyMismatch,
+    TlsCertificateExpired,
+    /// The certificate expires within `CERTIFICATE_EXPIRY_WARNING_DAYS`
+    TlsCertificateExpiring,
+    /// A MOH file does not exist
+    MohFileMissing,
+    /// A directory files are read from or written to does not exist
+    DirectoryMissing,
+    /// A trunk, peer or STUN host name does not resolve
+    HostUnresolvable,
+}
+
+impl PreflightCode {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::InvalidAddress => "invalid_address",
+            Self::AddressFamilyMismatch => "address_family_mismatch",
+            Self::PortConflict => "port_conflict",
+            Self::InvalidValue => "invalid_value",
+            Self::InvalidRules => "invalid_rules",
+            Self::DatabaseUrlInvalid => "database_url_invalid",
+            Self::DatabaseUnreachable => "database_unreachable",
+            Self::TlsSettingsMissing => "tls_settings_missing",
+            Self::TlsFileUnreadable => "tls_file_unreadable",
+            Self::TlsKeyMismatch => "tls_key_mismatch",
+            Self::TlsCertificateExpired => "tls_certificate_expired",
+            Self::TlsCertificateExpiring => "tls_certificate_expiring",
+            Self::MohFileMissing => "moh_file_missing",
+            Self::DirectoryMissing => "directory_missing",
+            Self::HostUnresolvable => "host_unresolvable",
+        }
+    }
+
+    pub fn severity(&self) -> Severity {
+        match self {
+            Self::TlsCertificateExpiring
+            | Self::MohFileMissing
+            | Self::DirectoryMissing
+            | Self::HostUnresolvable => Severity::Warning,
+            _ => Severity::Fatal,
+        }
+    }
+}
+
+/// One problem found in the configuration
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PreflightFinding {
+    pub code: PreflightCode,
+    pub severity: Severity,
+    /// Setting the finding is about, e.g. `sip.bind_port`
+    pub setting: String,
+    pub message: String,
+}
+
+impl fmt::Display for PreflightFinding {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let severity = match self.severity {
+            Severity::Warning => "warning",
+            Severity::Fatal => "fatal",
+        };
+        write!(
+            f,
+            "{} [{}] {}: {}",
+            severity,
+            self.code.as_str(),
+            self.setting,
+            self.message
+        )
+    }
+}
+
+/// Findings of a preflight run
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PreflightReport {
+    pub checked_at: DateTime<Utc>,
+    pub findings: Vec<PreflightFinding>,
+}
+
+impl PreflightReport {
+    /// Whether no finding is fatal
+    pub fn can_start(&self) -> bool {
+        self.fatal().next().is_none()
+    }
+
+    pub fn fatal(&self) -> impl Iterator<Item = &PreflightFinding> {
+        self.findings
+            .iter()
+            .filter(|finding| finding.severity == Severity::Fatal)
+    }
+
+    pub fn warnings(&self) -> impl Iterator<Item = &PreflightFinding> {
+        self.findings
+            .iter()
+            .filter(|finding| finding.severity == Severity::Warning)
+    }
+
+    pub fn has(&self, code: PreflightCode) -> bool {
+        self.findings.iter().any(|finding| finding.code == code)
+    }
+
+    fn add(&mut self, code: PreflightCode, setting: &str, message: impl Into<String>) {
+        self.findings.push(PreflightFinding {
+            code,
+            severity: code.severity(),
+            setting: setting.to_string(),
+            message: message.into(),
+        });
+    }
+}
+
+impl fmt::Display for PreflightReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for finding in self.fatal().chain(self.warnings()) {
+            writeln!(f, "{}", finding)?;
+        }
+        write!(
+            f,
+            "{} fatal, {} warnings",
+            self.fatal().count(),
+            self.warnings().count()
+        )
+    }
+}
+
+/// Transport a listener uses; TCP and UDP listeners may share a port
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Transport {
+    Udp,
+    Tcp,
+}
+
+/// Port range a service listens on
+struct Listener {
+    setting: &'static str,
+    address: Option<IpAddr>,
+    ports: (u16, u16),
+    transports: &'static [Transport],
+}
+
+impl Listener {
+    fn conflicts_with(&self, other: &Listener) -> bool {
+        let addresses_overlap = match (self.address, other.address) {
+            (Some(a), Some(b)) => a == b || a.is_unspecified() || b.is_unspecified(),
+            _ => true,
+        };
+        let transports_overlap = self
+            .transports
+            .iter()
+            .any(|transport| other.transports.contains(transport));
+        addresses_overlap
+            && transports_overlap
+            && self.ports.0 <= other.ports.1
+            && other.ports.0 <= self.ports.1
+    }
+
+    fn describe_ports(&self) -> String {
+        if self.ports.0 == self.ports.1 {
+            format!("port {}", self.ports.0)
+        } else {
+            format!("ports {}-{}", self.ports.0, self.ports.1)
+        }
+    }
+}
+
+/// Checks a configuration before startup
+pub struct Preflight<'a> {
+    config: &'a Config,
+    check_database: bool,
+    now: DateTime<Utc>,
+}
+
+impl<'a> Preflight<'a> {
+    /// The database is checked when the server is built with PostgreSQL
+    pub fn new(config: &'a Config) -> Self {
+        Self {
+            config,
+            check_database: cfg!(feature = "postgres"),
+            now: Utc::now(),
+        }
+    }
+
+    /// Leave out the database connection check
+    pub fn without_database(mut self) -> Self {
+        self.check_database = false;
+        self
+    }
+
+    /// Check certificate expiry as of `now`
+    pub fn at(mut self, now: DateTime<Utc>) -> Self {
+        self.now = now;
+        self
+    }
+
+    pub async fn run(&self) -> PreflightReport {
+        let mut report = PreflightReport {
+            checked_at: Utc::now(),
+            findings: Vec::new(),
+        };
+
+        self.check_listeners(&mut report);
+        self.check_values(&mut report);
+        self.check_rules(&mut report);
+        self.check_files(&mut report);
+        if self.config.replication.enabled {
+            match &self.config.replication.tls {
+                Some(tls) => self.check_tls(tls, &mut report),
+                None => report.add(
+                    PreflightCode::TlsSettingsMissing,
+                    "replication.tls",
+                    "Replication is enabled but has no TLS settings",
+                ),
+            }
+        }
+        let trunk_hosts = self.check_database(&mut report).await;
+        self.check_hosts(trunk_hosts, &mut report).await;
+
+        report
+    }
+
+    fn check_listeners(&self, report: &mut PreflightReport) {
+        let config = self.config;
+        let mut listeners = Vec::new();
+
+        let api_address = config.server.host.parse::<IpAddr>().ok();
+        if config.server.port == 0 {
+            report.add(
+                PreflightCode::InvalidValue,
+                "server.port",
+                "API port must not be 0",
+            );
+        } else {
+            listeners.push(Listener {
+                setting: "server.port",
+                address: api_address,
+                ports: (config.server.port, config.server.port),
+                transports: &[Transport::Tcp],
+            });
+        }
+
+        let sip_address = match config.sip.bind_address.parse::<IpAddr>() {
+            Ok(address) => Some(address),
+            Err(_) => {
+                report.add(
+                    PreflightCode::InvalidAddress,
+                    "sip.bind_address",
+                    format!("Not an IP address: {}", config.sip.bind_address),
+                );
+                None
+            }
+        };
+        let sip_address_v6 = config.sip.bind_address_v6.as_ref().and_then(|address| {
+            match address.parse::<IpAddr>() {
+                Ok(IpAddr::V6(address)) => Some(IpAddr::V6(address)),
+                Ok(IpAddr::V4(_)) => {
+                    report.add(
+                        PreflightCode::AddressFamilyMismatch,
+                        "sip.bind_address_v6",
+                        format!("{} is not an IPv6 address", address),
+                    );
+                    None
+                }
+                Err(_) => {
+                    report.add(
+                        PreflightCode::InvalidAddress,
+                        "sip.bind_address_v6",
+                        format!("Not an IP address: {}", address),
+                    );
+                    None
+                }
+            }
+        });
+
+        if let Some(advertised) = config.sip.external_address.advertised_address {
+            let listens_v6 = sip_address_v6.is_some() || matches!(sip_address, Some(IpAddr::V6(_)));
+            let listens_v4 = matches!(sip_address, Some(IpAddr::V4(_)))
+                || matches!(sip_address, Some(IpAddr::V6(address)) if address.is_unspecified());
+            let mismatch = match advertised {
+                IpAddr::V4(_) => sip_address.is_some() && !listens_v4,
+                IpAddr::V6(_) => sip_address.is_some() && !listens_v6,
+            };
+            if mismatch {
+                report.add(
+                    PreflightCode::AddressFamilyMismatch,
+                    "sip.external_address.advertised_address",
+                    format!(
+                        "{} is advertised but SIP does not listen on an address of its family",
+                        advertised
+                    ),
+                );
+            }
+        }
+
+        if config.sip.bind_port == 0 {
+            report.add(
+                PreflightCode::InvalidValue,
+                "sip.bind_port",
+                "SIP port must not be 0",
+            );
+        } else {
+            for (setting, address) in [
+                ("sip.bind_port", sip_address),
+                ("sip.bind_address_v6", sip_address_v6),
+            ] {
+                if setting == "sip.bind_address_v6" && address.is_none() {
+                    continue;
+                }
+                listeners.push(Listener {
+                    setting,
+                    address,
+                    ports: (config.sip.bind_port, config.sip.bind_port),
+                    transports: &[Transport::Udp, Transport::Tcp],
+                });
+            }
+        }
+
+        // Media is bound on every address
+        listeners.push(Listener {
+            setting: "sip.rtp_port_min",
+            address: None,
+            ports: (config.sip.rtp_port_min, config.sip.rtp_port_max),
+            transports: &[Transport::Udp],
+        });
+
+        if config.replication.enabled {
+            match config.replication.listen_address.parse::<SocketAddr>() {
+                Ok(address) => listeners.push(Listener {
+                    setting: "replication.listen_address",
+                    address: Some(address.ip()),
+                    ports: (address.port(), address.port()),
+                    transports: &[Transport::Tcp],
+                }),
+                Err(_) => report.add(
+                    PreflightCode::InvalidAddress,
+                    "replication.listen_address",
+                    format!(
+                        "Not an address and port: {}",
+                        config.replication.listen_address
+                    ),
+                ),
+            }
+        }
+
+        for (i, a) in listeners.iter().enumerate() {
+            for b in &listeners[i + 1..] {
+                if a.conflicts_with(b) {
+                    report.add(
+                        PreflightCode::PortConflict,
+                        b.setting,
+                        format!(
+                            "{} collides with {} of {}",
+                            b.describe_ports(),
+                            a.describe_ports(),
+                            a.setting
+                        ),
+                    );
+                }
+            }
+        }
+    }
+
+    fn check_values(&self, report: &mut PreflightReport) {
+        let config = self.config;
+        for (setting, problem) in config.problems() {
+            report.add(PreflightCode::InvalidValue, setting, problem);
+        }
+        if config.media.min_ptime_ms > config.media.max_ptime_ms {
+            report.add(
+                PreflightCode::InvalidValue,
+                "media.min_ptime_ms",
+                format!(
+                    "Minimum packet time {}ms is above the maximum {}ms",
+                    config.media.min_ptime_ms, config.media.max_ptime_ms
+                ),
+            );
+        }
+        if let Err(e) = config.media.capacity.validate() {
+            report.add(PreflightCode::InvalidValue, "media.capacity", e);
+        }
+        if let Err(e) = config.media.bandwidth.validate() {
+            report.add(PreflightCode::InvalidValue, "media.bandwidth", e);
+        }
+        if let Err(e) = config.media.moh.validate() {
+            report.add(PreflightCode::InvalidValue, "media.moh", e);
+        }
+        let pagination = &config.server.pagination;
+        if pagination.default_limit < 1 || pagination.default_limit > pagination.max_limit {
+            report.add(
+                PreflightCode::InvalidValue,
+                "server.pagination.default_limit",
+                format!(
+                    "Default page size {} must be between 1 and the maximum {}",
+                    pagination.default_limit, pagination.max_limit
+                ),
+            );
+        }
+        if let Err(e) = config.surveys.validate() {
+            report.add(PreflightCode::InvalidValue, "surveys", e);
+        }
+        if let Err(e) = config.time_zones.validate() {
+            report.add(PreflightCode::InvalidValue, "time_zones", e);
+        }
+        if let Err(e) = config.feature_codes.validate() {
+            report.add(PreflightCode::InvalidValue, "feature_codes", e.to_string());
+        }
+        if let Err(e) = config.provisioning.validate() {
+            report.add(PreflightCode::InvalidValue, "provisioning.devices", e);
+        }
+        if let Err(e) = config.enrollment.validate() {
+            report.add(PreflightCode::InvalidValue, "enrollment", e);
+        }
+        if let Err(e) = config.scheduled_calls.validate() {
+            report.add(PreflightCode::InvalidValue, "scheduled_calls", e);
+        }
+        if let Err(e) = config.telemetry.validate() {
+            report.add(PreflightCode::InvalidValue, "telemetry", e);
+        }
+        if let Err(e) = config.storage.validate() {
+            report.add(PreflightCode::InvalidValue, "storage", e);
+        }
+        if let Err(e) = config.setup_latency.validate() {
+            report.add(PreflightCode::InvalidValue, "setup_latency", e);
+        }
+        if let Err(e) = config.lnp.validate() {
+            report.add(PreflightCode::InvalidValue, "lnp", e);
+        }
+        if let Err(e) = config.answer_supervision.validate() {
+            report.add(PreflightCode::InvalidValue, "answer_supervision", e);
+        }
+        if let Err(e) = config.sequences.validate() {
+            report.add(PreflightCode::InvalidValue, "sequences", e);
+        }
+        if let Err(e) = config.privacy.validate() {
+            report.add(PreflightCode::InvalidValue, "privacy", e);
+        }
+        if let Err(e) = config.secrets.validate() {
+            report.add(PreflightCode::InvalidValue, "secrets", e);
+        }
+        if let Err(e) = config.server.idempotency.validate() {
+            report.add(PreflightCode::InvalidValue, "server.idempotency", e);
+        }
+        if let Err(e) = config.class_of_service.validate() {
+            report.add(PreflightCode::InvalidValue, "class_of_service", e);
+        }
+        if let Err(e) = config.sip.dns.validate() {
+            report.add(PreflightCode::InvalidValue, "sip.dns", e);
+        }
+        if let Err(e) = config.sip.timers.validate() {
+            report.add(PreflightCode::InvalidValue, "sip.timers", e);
+        }
+        if let Err(e) = config.sip.overload.validate() {
+            report.add(PreflightCode::InvalidValue, "sip.overload", e);
+        }
+        if let Err(e) = config.sip.survivability.validate() {
+            report.add(PreflightCode::InvalidValue, "sip.survivability", e);
+        }
+        if let Err(e) = config.sip.trunk_channels.validate() {
+            report.add(PreflightCode::InvalidValue, "sip.trunk_channels", e);
+        }
+        if let Err(e) = config.sip.srtp_rekey.validate() {
+            report.add(PreflightCode::InvalidValue, "sip.srtp_rekey", e);
+        }
+        if let Err(e) = config.sip.codec_fallback.validate() {
+            report.add(PreflightCode::InvalidValue, "sip.codec_fallback", e);
+        }
+        if let Err(e) = config.sip.warm_transfer.validate() {
+            report.add(PreflightCode::InvalidValue, "sip.warm_transfer", e);
+        }
+        let url = &config.database.url;
+        if !(url.starts_with("postgres://") || url.starts_with("postgresql://")) {
+            report.add(
+                PreflightCode::DatabaseUrlInvalid,
+                "database.url",
+                "Database URL must start with postgres:// or postgresql://",
+            );
+        }
+    }
+
+    fn check_rules(&self, report: &mut PreflightReport) {
+        let config = self.config;
+        if let Err(e) = QuirksRegistry::new(config.sip.quirks.clone()) {
+            report.add(PreflightCode::InvalidRules, "sip.quirks", e);
+        }
+        if let Err(e) = HeaderRulesEngine::new(config.sip.header_rules.clone()) {
+            report.add(PreflightCode::InvalidRules, "sip.header_rules", e.to_string());
+        }
+        if let Err(e) = AddressAdvertiser::from_config(&config.sip.external_address) {
+            report.add(PreflightCode::InvalidRules, "sip.external_address", e);
+        }
+        for (realm, tenant) in &config.branding.tenants {
+            if let Err(e) = tenant.validate() {
+                report.add(
+                    PreflightCode::InvalidValue,
+                    &format!("branding.tenants.{}", realm),
+                    e,
+                );
+            }
+        }
+    }
+
+    fn check_files(&self, report: &mut PreflightReport) {
+        let config = self.config;
+        if !Path::new(&config.audio.library_root).is_dir() {
+            report.add(
+                PreflightCode::DirectoryMissing,
+                "audio.library_root",
+                format!("{} does not exist", config.audio.library_root),
+            );
+        }
+        if !Path::new(&config.branding.default_moh_source).is_file() {
+            report.add(
+                PreflightCode::MohFileMissing,
+                "branding.default_moh_source",
+                format!(
+                    "{} does not exist; callers on hold hear silence",
+                    config.branding.default_moh_source
+                ),
+            );
+        }
+        if config.fraud.enabled {
+            let state_dir = Path::new(&config.fraud.state_path)
+                .parent()
+                .filter(|dir| !dir.as_os_str().is_empty());
+            if let Some(dir) = state_dir {
+                if !dir.is_dir() {
+                    report.add(
+                        PreflightCode::DirectoryMissing,
+                        "fraud.state_path",
+                        format!("{} does not exist; counters are not saved", dir.display()),
+                    );
+                }
+            }
+        }
+    }
+
+    fn check_tls(&self, tls: &ReplicationTlsConfig, report: &mut PreflightReport) {
+        let chain = load_certs(&tls.cert_path)
+            .map_err(|e| report.add(PreflightCode::TlsFileUnreadable, "replication.tls.cert_path", e))
+            .ok();
+        let key = load_key(&tls.key_path)
+            .map_err(|e| report.add(PreflightCode::TlsFileUnreadable, "replication.tls.key_path", e))
+            .ok();
+        if let Err(e) = load_certs(&tls.ca_path) {
+            report.add(PreflightCode::TlsFileUnreadable, "replication.tls.ca_path", e);
+        }
+
+        let Some(chain) = chain else {
+            return;
+        };
+        match x509_parser::parse_x509_certificate(&chain[0]) {
+            Ok((_, certificate)) => {
+                let not_after = certificate.validity().not_after;
+                let expires_at = DateTime::from_timestamp(not_after.timestamp(), 0);
+                match expires_at {
+                    Some(expires_at) if expires_at <= self.now => report.add(
+                        PreflightCode::TlsCertificateExpired,
+                        "replication.tls.cert_path",
+                        format!("{} expired on {}", tls.cert_path, expires_at.to_rfc3339()),
+                    ),
+                    Some(expires_at)
+                        if expires_at - self.now
+                            < chrono::Duration::days(CERTIFICATE_EXPIRY_WARNING_DAYS) =>
+                    {
+                        report.add(
+                            PreflightCode::TlsCertificateExpiring,
+                            "replication.tls.cert_path",
+                            format!("{} expires on {}", tls.cert_path, expires_at.to_rfc3339()),
+                        )
+                    }
+                    _ => {}
+                }
+            }
+            Err(e) => report.add(
+                PreflightCode::TlsFileUnreadable,
+                "replication.tls.cert_path",
+                format!("Invalid certificate in {}: {}", tls.cert_path, e),
+            ),
+        }
+
+        if let Some(key) = key {
+            let built = ServerConfig::builder_with_provider(crypto_provider())
+                .with_safe_default_protocol_versions()
+                .and_then(|builder| builder.with_no_client_auth().with_single_cert(chain, key));
+            if let Err(e) = built {
+                report.add(
+                    PreflightCode::TlsKeyMismatch,
+                    "replication.tls.key_path",
+                    format!(
+                        "{} does not match the certificate in {}: {}",
+                        tls.key_path, tls.cert_path, e
+                    ),
+                );
+            }
+        }
+    }
+
+    /// Connect to the database; returns the hosts of the stored trunks
+    #[cfg(feature = "postgres")]
+    async fn check_database(&self, report: &mut PreflightReport) -> Vec<(String, String)> {
+        use crate::domain::sip_trunk::SipTrunkRepository;
+        use crate::infrastructure::persistence::PgSipTrunkRepository;
+        use crate::infrastructure::secrets::SecretKeyring;
+        use sqlx::postgres::PgPoolOptions;
+
+        if !self.check_database || report.has(PreflightCode::DatabaseUrlInvalid) {
+            return Vec::new();
+        }
+        let pool = match PgPoolOptions::new()
+            .max_connections(1)
+            .acquire_timeout(CHECK_TIMEOUT)
+            .connect(&self.config.database.url)
+            .await
+        {
+            Ok(pool) => pool,
+            Err(e) => {
+                report.add(
+                    PreflightCode::DatabaseUnreachable,
+                    "database.url",
+                    format!("Cannot connect to the database: {}", e),
+                );
+                return Vec::new();
+            }
+        };
+
+        // Missing before the first migration; nothing to resolve then
+        let mut trunks = PgSipTrunkRepository::new(pool.clone());
+        if let Ok(Some(keyring)) = SecretKeyring::from_config(&self.config.secrets) {
+            trunks = trunks.with_keyring(std::sync::Arc::new(keyring));
+        }
+        let trunks = trunks
+            .list_trunks(true)
+            .await
+            .unwrap_or_default();
+        pool.close().await;
+        trunks
+            .into_iter()
+            .flat_map(|trunk| {
+                let setting = format!("trunks.{}", trunk.name);
+                let port = trunk.sip_port;
+                std::iter::once(format!("{}:{}", trunk.sip_server, port))
+                    .chain(trunk.backup_server.map(|backup| with_port(&backup, port)))
+                    .map(move |host| (setting.clone(), host))
+            })
+            .collect()
+    }
+
+    #[cfg(not(feature = "postgres"))]
+    async fn check_database(&self, _report: &mut PreflightReport) -> Vec<(String, String)> {
+        Vec::new()
+    }
+
+    async fn check_hosts(&self, trunk_hosts: Vec<(String, String)>, report: &mut PreflightReport) {
+        let config = self.config;
+        let mut hosts = trunk_hosts;
+        for (name, trunk) in &config.sip.header_rules.trunks {
+            for host in &trunk.hosts {
+                hosts.push((
+                    format!("sip.header_rules.trunks.{}", name),
+                    with_port(host, DEFAULT_SIP_PORT),
+                ));
+            }
+        }
+        if let Some(stun_server) = &config.sip.external_address.stun_server {
+            hosts.push((
+                "sip.external_address.stun_server".to_string(),
+                stun_server.clone(),
+            ));
+        }
+        if config.replication.enabled {
+            if let Some(peer) = &config.replication.peer_address {
+                hosts.push(("replication.peer_address".to_string(), peer.clone()));
+            }
+        }
+
+        for (setting, host) in hosts {
+            if host.parse::<SocketAddr>().is_ok() {
+                continue;
+            }
+            let resolved = tokio::time::timeout(CHECK_TIMEOUT, tokio::net::lookup_host(&host))
+                .await
+                .ok()
+                .and_then(Result::ok)
+                .is_some_and(|mut addresses| addresses.next().is_some());
+            if !resolved {
+                report.add(
+                    PreflightCode::HostUnresolvable,
+                    &setting,
+                    format!("{} does not resolve", host),
+                );
+            }
+        }
+    }
+}
+
+/// `host:port`, keeping a port `host` already has
+fn with_port(host: &str, port: u16) -> String {
+    if host.parse::<SocketAddr>().is_ok() {
+        return host.to_string();
+    }
+    if let Ok(address) = host.parse::<IpAddr>() {
+        return SocketAddr::new(address, port).to_string();
+    }
+    match host.rsplit_once(':') {
+        Some((_, port)) if port.parse::<u16>().is_ok() => host.to_string(),
+        _ => format!("{}:{}", host, port),
+    }
+}
+
+fn load_certs(path: &str) -> Result<Vec<CertificateDer<'static>>, String> {
+    let file = File::open(path).map_err(|e| format!("Cannot read {}: {}", path, e))?;
+    let chain = certs(&mut BufReader::new(file))
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|e| format!("Invalid certificates in {}: {}", path, e))?;
+    if chain.is_empty() {
+        return Err(format!("No certificates in {}", path));
+    }
+    Ok(chain)
+}
+
+fn load_key(path: &str) -> Result<PrivateKeyDer<'static>, String> {
+    let file = File::open(path).map_err(|e| format!("Cannot read {}: {}", path, e))?;
+    private_key(&mut BufReader::new(file))
+        .map_err(|e| format!("Invalid private key in {}: {}", path, e))?
+        .ok_or_else(|| format!("No private key in {}", path))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::infrastructure::protocols::sip::TrunkHeaderRules;
+    use chrono::TimeZone;
+    use rcgen::{date_time_ymd, CertificateParams, KeyPair};
+    use std::path::PathBuf;
+    use uuid::Uuid;
+
+    fn temp_dir() -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("yakyak-preflight-{}", Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    /// Writes a self-signed certificate valid until `not_after` and its key
+    fn write_certificate(dir: &Path, name: &str, not_after: (i32, u8, u8)) -> (String, String) {
+        let mut params = CertificateParams::new(vec!["pbx.example.com".to_string()]).unwrap();
+        params.not_before = date_time_ymd(2020, 1, 1);
+        params.not_after = date_time_ymd(not_after.0, not_after.1, not_after.2);
+        let key = KeyPair::generate().unwrap();
+        let certificate = params.self_signed(&key).unwrap();
+
+        let cert_path = dir.join(format!("{}.crt", name));
+        let key_path = dir.join(format!("{}.key", name));
+        std::fs::write(&cert_path, certificate.pem()).unwrap();
+        std::fs::write(&key_path, key.serialize_pem()).unwrap();
+        (
+            cert_path.to_string_lossy().to_string(),
+            key_path.to_string_lossy().to_string(),
+        )
+    }
+
+    /// A config whose files exist and whose ports are apart
+    fn sound_config(dir: &Path) -> Config {
+        let moh = dir.join("default.wav");
+        std::fs::write(&moh, b"RIFF").unwrap();
+
+        let mut config = Config::default();
+        config.audio.library_root = dir.to_string_lossy().to_string();
+        config.branding.default_moh_source = moh.to_string_lossy().to_string();
+        config.fraud.state_path = dir.join("fraud.json").to_string_lossy().to_string();
+        config
+    }
+
+    fn codes(report: &PreflightReport) -> Vec<PreflightCode> {
+        report.findings.iter().map(|finding| finding.code).collect()
+    }
+
+    #[tokio::test]
+    async fn test_sound_config_passes() {
+        let dir = temp_dir();
+        let config = sound_config(&dir);
+
+        let report = Preflight::new(&config).without_database().run().await;
+        assert!(report.findings.is_empty(), "{}", report);
+        assert!(report.can_start());
+    }
+
+    #[tokio::test]
+    async fn test_all_problems_reported() {
+        let dir = temp_dir();
+        let mut config = sound_config(&dir);
+        // SIP inside the RTP range, IPv4 where IPv6 belongs
+        config.sip.bind_port = 10_500;
+        config.sip.bind_address_v6 = Some("192.0.2.1".to_string());
+        config.media.min_ptime_ms = 80;
+        config.branding.default_moh_source = dir.join("missing.wav").to_string_lossy().to_string();
+        config.database.url = "postgres://yakyak@127.0.0.1:1/yakyak".to_string();
+
+        // Expired certificate with another certificate's key
+        let (cert_path, _) = write_certificate(&dir, "expired", (2021, 1, 1));
+        let (ca_path, other_key) = write_certificate(&dir, "ca", (2099, 1, 1));
+        config.replication.enabled = true;
+        config.replication.listen_address = format!("0.0.0.0:{}", config.server.port);
+        config.replication.tls = Some(ReplicationTlsConfig {
+            cert_path,
+            key_path: other_key,
+            ca_path,
+            server_name: "pbx.example.com".to_string(),
+        });
+
+        let report = Preflight::new(&config).run().await;
+        let codes = codes(&report);
+        for code in [
+            PreflightCode::PortConflict,
+            PreflightCode::AddressFamilyMismatch,
+            PreflightCode::InvalidValue,
+            PreflightCode::MohFileMissing,
+            PreflightCode::TlsCertificateExpired,
+            PreflightCode::TlsKeyMismatch,
+        ] {
+            assert!(codes.contains(&code), "{:?} missing from\n{}", code, report);
+        }
+        if cfg!(feature = "postgres") {
+            assert!(codes.contains(&PreflightCode::DatabaseUnreachable), "{}", report);
+        }
+
+        // Both collisions: SIP with RTP, replication with the API
+        let conflicts: Vec<&str> = report
+            .findings
+            .iter()
+            .filter(|finding| finding.code == PreflightCode::PortConflict)
+            .map(|finding| finding.setting.as_str())
+            .collect();
+        assert!(conflicts.contains(&"sip.rtp_port_min"), "{}", report);
+        assert!(conflicts.contains(&"replication.listen_address"), "{}", report);
+
+        assert!(!report.can_start());
+        let finding = report
+            .findings
+            .iter()
+            .find(|finding| finding.code == PreflightCode::MohFileMissing)
+            .unwrap();
+        assert_eq!(finding.severity, Severity::Warning);
+        assert!(report.to_string().contains("fatal [tls_certificate_expired]"));
+    }
+
+    #[tokio::test]
+    async fn test_every_invalid_setting_reported() {
+        let dir = temp_dir();
+        let mut config = sound_config(&dir);
+        config.sip.domain = String::new();
+        config.database.url = String::new();
+
+        let report = Preflight::new(&config).without_database().run().await;
+        let invalid: Vec<&str> = report
+            .findings
+            .iter()
+            .filter(|finding| finding.code == PreflightCode::InvalidValue)
+            .map(|finding| finding.setting.as_str())
+            .collect();
+        assert!(invalid.contains(&"sip.domain"), "{}", report);
+        assert!(invalid.contains(&"database.url"), "{}", report);
+        assert!(!report.can_start());
+    }
+
+    #[tokio::test]
+    async fn test_warnings_do_not_prevent_startup() {
+        let dir = temp_dir();
+        let mut config = sound_config(&dir);
+        config.audio.library_root = dir.join("library").to_string_lossy().to_string();
+        config.branding.default_moh_source = dir.join("missing.wav").to_string_lossy().to_string();
+        config.sip.header_rules.trunks.insert(
+            "carrier".to_string(),
+            TrunkHeaderRules {
+                hosts: vec!["sip.carrier.invalid".to_string(), "192.0.2.50".to_string()],
+                ..Default::default()
+            },
+        );
+
+        // Valid for another week only
+        let (cert_path, key_path) = write_certificate(&dir, "node", (2026, 3, 8));
+        config.replication.enabled = true;
+        config.replication.tls = Some(ReplicationTlsConfig {
+            cert_path: cert_path.clone(),
+            key_path,
+            ca_path: cert_path,
+            server_name: "pbx.example.com".to_string(),
+        });
+
+        let now = Utc.with_ymd_and_hms(2026, 3, 1, 0, 0, 0).unwrap();
+        let report = Preflight::new(&config).without_database().at(now).run().await;
+        assert_eq!(
+            codes(&report),
+            vec![
+                PreflightCode::DirectoryMissing,
+                PreflightCode::MohFileMissing,
+                PreflightCode::TlsCertificateExpiring,
+                PreflightCode::HostUnresolvable,
+            ],
+            "{}",
+            report
+        );
+        assert!(report.can_start());
+        assert_eq!(report.warnings().count(), 4);
+    }
+}
@@ -0,0 +1,147 @@
+//! Numbers and times read out by concatenating prompts
+//!
+//! A phone number is read digit by digit (`digit_4`, `digit_2`, ...). Times
+//! use the `number_<n>` prompts of the call announcer: English reads a
+//! 12-hour clock ("two oh five pm"), other languages a 24-hour clock
+//! followed by their word for hours, and Chinese, Japanese and Korean also
+//! name the minutes ("14 点 5 分").
+
+use super::manager::{AudioFileManager, Language};
+use super::wav::{WavFile, NARROWBAND_RATE};
+use chrono::{NaiveTime, Timelike};
+
+/// Prompts reading `number` digit by digit; characters other than digits,
+/// `+`, `*` and `#` are skipped
+pub fn digit_prompts(number: &str) -> Vec<String> {
+    number
+        .chars()
+        .filter_map(|c| match c {
+            '0'..='9' => Some(format!("digit_{}", c)),
+            '+' => Some("digit_plus".to_string()),
+            '*' => Some("digit_star".to_string()),
+            '#' => Some("digit_pound".to_string()),
+            _ => None,
+        })
+        .collect()
+}
+
+/// Prompts reading the time of day in `language`
+pub fn time_prompts(time: NaiveTime, language: Language) -> Vec<String> {
+    let number = |n: u32| format!("number_{}", n);
+    let (hour, minute) = (time.hour(), time.minute());
+    match language {
+        Language::En => {
+            let mut prompts = vec![number(match hour % 12 {
+                0 => 12,
+                hour => hour,
+            })];
+            match minute {
+                0 => prompts.push("time_oclock".to_string()),
+                1..=9 => prompts.extend(["time_oh".to_string(), number(minute)]),
+                _ => prompts.push(number(minute)),
+            }
+            prompts.push(if hour < 12 { "time_am" } else { "time_pm" }.to_string());
+            prompts
+        }
+        Language::Zh | Language::Ja | Language::Ko => {
+            let mut prompts = vec![number(hour), "time_hours".to_string()];
+            if minute > 0 {
+                prompts.extend([number(minute), "time_minutes".to_string()]);
+            }
+            prompts
+        }
+        _ => {
+            let mut prompts = vec![number(hour), "time_hours".to_string()];
+            if minute > 0 {
+                prompts.push(number(minute));
+            }
+            prompts
+        }
+    }
+}
+
+/// The prompts `ids` in `language` as one 8 kHz recording
+///
+/// A prompt missing in `language` falls back to the manager's default
+/// language; one missing in both is an error.
+pub fn concat_prompts(
+    manager: &AudioFileManager,
+    ids: &[String],
+    language: Language,
+) -> Result<WavFile, String> {
+    let parts = ids
+        .iter()
+        .map(|id| {
+            manager
+                .get_with_fallback(id, language)
+                .map(|audio| audio.as_ref().clone())
+                .ok_or_else(|| format!("Audio file not found: {}", id))
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    Ok(WavFile::concat(&parts, NARROWBAND_RATE))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use uuid::Uuid;
+
+    #[test]
+    fn test_digit_prompts() {
+        assert_eq!(
+            digit_prompts("+1 (555) 20*#"),
+            vec![
+                "digit_plus", "digit_1", "digit_5", "digit_5", "digit_5", "digit_2", "digit_0",
+                "digit_star", "digit_pound",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_time_prompts_per_language() {
+        let time = |h, m| NaiveTime::from_hms_opt(h, m, 0).unwrap();
+        assert_eq!(
+            time_prompts(time(14, 5), Language::En),
+            vec!["number_2", "time_oh", "number_5", "time_pm"]
+        );
+        assert_eq!(
+            time_prompts(time(0, 0), Language::En),
+            vec!["number_12", "time_oclock", "time_am"]
+        );
+        assert_eq!(
+            time_prompts(time(14, 5), Language::De),
+            vec!["number_14", "time_hours", "number_5"]
+        );
+        assert_eq!(
+            time_prompts(time(9, 30), Language::Zh),
+            vec!["number_9", "time_hours", "number_30", "time_minutes"]
+        );
+    }
+
+    #[test]
+    fn test_concat_prompts_with_fallback() {
+        let dir = std::env::temp_dir().join(format!("yakyak-digits-{}", Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let mut manager = AudioFileManager::new(&dir);
+        for (id, language, sample) in [
+            ("digit_1", Language::Es, 100i16),
+            ("digit_1", Language::En, 200),
+            ("digit_2", Language::En, 300),
+        ] {
+            let path = dir.join(format!("{}-{}.wav", language.code(), id));
+            std::fs::write(&path, WavFile::from_pcm(NARROWBAND_RATE, &[sample; 80]).to_wav_bytes())
+                .unwrap();
+            manager.register(id, language, &path, None).unwrap();
+        }
+
+        // Spanish "1", then the English "2" for lack of a Spanish one
+        let audio = concat_prompts(&manager, &digit_prompts("12"), Language::Es).unwrap();
+        let samples = audio.samples_i16();
+        assert_eq!(samples.len(), 160);
+        assert!(samples[..80].iter().all(|&s| s == 100));
+        assert!(samples[80..].iter().all(|&s| s == 300));
+
+        assert!(concat_prompts(&manager, &digit_prompts("3"), Language::En).is_err());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,599 @@
+/// Runtime audio library: uploaded prompts, announcements and MOH per tenant
+///
+/// Files are stored under a configured root as
+/// `<root>/<tenant>/<category>/<sha256>.wav`, so uploading the same audio
+/// twice yields a single file. Components that play a file (MOH classes, IVR
+/// menus, voicemail greetings) register a reference so the file cannot be
+/// deleted out from under them.
+use crate::domain::audio::wav::{
+    TelephonyConverter, WavError, WavFile, WavReader, NARROWBAND_RATE,
+};
+use crate::domain::routing::lint::{EntityKind, Lint, RoutingInventory};
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::{HashMap, VecDeque};
+use std::io::Cursor;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+/// Directory used for files not owned by a tenant
+const GLOBAL_TENANT_DIR: &str = "_global";
+
+/// Metadata index kept next to the stored files
+const INDEX_FILE: &str = "index.json";
+
+/// Audio file category
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum AudioCategory {
+    /// Music on hold
+    Moh,
+    /// IVR / system prompt
+    Prompt,
+    /// Announcement (e.g. queue position, after-hours message)
+    Announcement,
+}
+
+impl AudioCategory {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            AudioCategory::Moh => "moh",
+            AudioCategory::Prompt => "prompt",
+            AudioCategory::Announcement => "announcement",
+        }
+    }
+}
+
+impl std::str::FromStr for AudioCategory {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "moh" => Ok(AudioCategory::Moh),
+            "prompt" => Ok(AudioCategory::Prompt),
+            "announcement" => Ok(AudioCategory::Announcement),
+            _ => Err(format!("Unknown audio category: {}", s)),
+        }
+    }
+}
+
+/// Something that uses an audio file
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct AudioReference {
+    /// Kind of user, e.g. "moh", "ivr", "voicemail_greeting"
+    pub kind: String,
+    /// Identifier of the user (MOH class, IVR menu id, mailbox)
+    pub owner: String,
+}
+
+impl AudioReference {
+    pub fn new(kind: &str, owner: &str) -> Self {
+        Self {
+            kind: kind.to_string(),
+            owner: owner.to_string(),
+        }
+    }
+}
+
+/// Stored audio file metadata
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct StoredAudio {
+    /// Content hash of the stored file (also its file name)
+    pub id: String,
+    pub tenant_id: Option<String>,
+    pub name: String,
+    pub category: AudioCategory,
+    #[serde(skip)]
+    pub path: PathBuf,
+    /// Duration in seconds
+    pub duration: f64,
+    /// Stored file size in bytes
+    pub size: u64,
+    pub sample_rate: u32,
+    pub channels: u16,
+    pub uploaded_at: DateTime<Utc>,
+}
+
+/// Audio library errors
+#[derive(Debug, Clone, PartialEq)]
+pub enum AudioLibraryError {
+    /// Upload is not a usable WAV file
+    Invalid(WavError),
+    /// File is not 8 kHz mono and conversion is disabled
+    UnsupportedFormat { sample_rate: u32, channels: u16 },
+    NotFound(String),
+    /// File is still referenced
+    InUse(Vec<AudioReference>),
+    Io(String),
+}
+
+impl std::fmt::Display for AudioLibraryError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            AudioLibraryError::Invalid(e) => write!(f, "Invalid WAV file: {}", e),
+            AudioLibraryError::UnsupportedFormat { sample_rate, channels } => write!(
+                f,
+                "Unsupported format {} Hz / {} channel(s); expected 8000 Hz mono",
+                sample_rate, channels
+            ),
+            AudioLibraryError::NotFound(id) => write!(f, "Audio file not found: {}", id),
+            AudioLibraryError::InUse(refs) => {
+                write!(f, "Audio file is referenced by {} user(s)", refs.len())
+            }
+            AudioLibraryError::Io(e) => write!(f, "I/O error: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for AudioLibraryError {}
+
+/// Audio library configuration
+#[derive(Debug, Clone)]
+pub struct AudioLibraryConfig {
+    /// Root directory for stored files
+    pub root: PathBuf,
+    /// Downmix/resample uploads to 8 kHz mono instead of rejecting them
+    pub auto_convert: bool,
+    /// Maximum number of decoded files kept in memory
+    pub pcm_cache_capacity: usize,
+}
+
+impl Default for AudioLibraryConfig {
+    fn default() -> Self {
+        Self {
+            root: PathBuf::from("audio/library"),
+            auto_convert: true,
+            pcm_cache_capacity: 32,
+        }
+    }
+}
+
+/// Bounded LRU cache of decoded PCM samples
+struct PcmCache {
+    capacity: usize,
+    entries: HashMap<String, Arc<Vec<i16>>>,
+    order: VecDeque<String>,
+}
+
+impl PcmCache {
+    fn new(capacity: usize) -> Self {
+        Self {
+            capacity: capacity.max(1),
+            entries: HashMap::new(),
+            order: VecDeque::new(),
+        }
+    }
+
+    fn get(&mut self, key: &str) -> Option<Arc<Vec<i16>>> {
+        let samples = self.entries.get(key).cloned()?;
+        self.order.retain(|k| k != key);
+        self.order.push_back(key.to_string());
+        Some(samples)
+    }
+
+    fn insert(&mut self, key: String, samples: Arc<Vec<i16>>) {
+        if !self.entries.contains_key(&key) && self.entries.len() >= self.capacity {
+            if let Some(oldest) = self.order.pop_front() {
+                self.entries.remove(&oldest);
+            }
+        }
+        self.order.retain(|k| *k != key);
+        self.order.push_back(key.clone());
+        self.entries.insert(key, samples);
+    }
+
+    fn remove(&mut self, key: &str) {
+        self.entries.remove(key);
+        self.order.retain(|k| k != key);
+    }
+}
+
+/// Key of a stored file: tenant plus content hash
+type FileKey = (Option<String>, String);
+
+/// Runtime audio file library
+pub struct AudioLibrary {
+    config: AudioLibraryConfig,
+    files: Arc<Mutex<HashMap<FileKey, StoredAudio>>>,
+    references: Arc<Mutex<HashMap<FileKey, Vec<AudioReference>>>>,
+    pcm_cache: Mutex<PcmCache>,
+}
+
+impl AudioLibrary {
+    /// Create a library, loading the metadata index from `config.root` if present
+    pub fn new(config: AudioLibraryConfig) -> Self {
+        let pcm_cache = Mutex::new(PcmCache::new(config.pcm_cache_capacity));
+        let library = Self {
+            config,
+            files: Arc::new(Mutex::new(HashMap::new())),
+            references: Arc::new(Mutex::new(HashMap::new())),
+            pcm_cache,
+        };
+        library.load_index();
+        library
+    }
+
+    fn load_index(&self) {
+        let index_path = self.config.root.join(INDEX_FILE);
+        let entries: Vec<StoredAudio> = match std::fs::read(&index_path) {
+            Ok(bytes) => serde_json::from_slice(&bytes).unwrap_or_default(),
+            Err(_) => return,
+        };
+
+        let mut files = self.files.lock().unwrap();
+        for mut entry in entries {
+            entry.path = self
+                .directory(entry.tenant_id.as_deref(), entry.category)
+                .join(format!("{}.wav", entry.id));
+            if entry.path.exists() {
+                files.insert(Self::key(entry.tenant_id.as_deref(), &entry.id), entry);
+            }
+        }
+    }
+
+    fn save_index(&self) -> Result<(), AudioLibraryError> {
+        let entries: Vec<StoredAudio> = self.files.lock().unwrap().values().cloned().collect();
+        let json = serde_json::to_vec_pretty(&entries)
+            .map_err(|e| AudioLibraryError::Io(e.to_string()))?;
+        std::fs::create_dir_all(&self.config.root)
+            .and_then(|_| std::fs::write(self.config.root.join(INDEX_FILE), json))
+            .map_err(|e| AudioLibraryError::Io(e.to_string()))
+    }
+
+    /// Get the configuration
+    pub fn config(&self) -> &AudioLibraryConfig {
+        &self.config
+    }
+
+    fn key(tenant_id: Option<&str>, id: &str) -> FileKey {
+        (tenant_id.map(|t| t.to_string()), id.to_string())
+    }
+
+    fn directory(&self, tenant_id: Option<&str>, category: AudioCategory) -> PathBuf {
+        self.config
+            .root
+            .join(tenant_id.unwrap_or(GLOBAL_TENANT_DIR))
+            .join(category.as_str())
+    }
+
+    /// Validate, normalize and store an uploaded WAV file
+    ///
+    /// Uploading identical audio again returns the existing entry.
+    pub fn upload(
+        &self,
+        tenant_id: Option<&str>,
+        name: &str,
+        category: AudioCategory,
+        data: &[u8],
+    ) -> Result<StoredAudio, AudioLibraryError> {
+        let source = WavReader::new(Cursor::new(data)).map_err(AudioLibraryError::Invalid)?;
+        let format = source.format().clone();
+
+        let is_telephony = format.sample_rate == NARROWBAND_RATE && format.channels == 1;
+        let wav = if self.config.auto_convert {
+            // Downmixed, resampled and made 16-bit block by block
+            WavFile::from_reader_converted(source, NARROWBAND_RATE)
+        } else if is_telephony {
+            WavFile::from_wav_reader(source)
+        } else {
+            return Err(AudioLibraryError::UnsupportedFormat {
+                sample_rate: format.sample_rate,
+                channels: format.channels,
+            });
+        }
+        .map_err(AudioLibraryError::Invalid)?;
+
+        if wav.data.is_empty() {
+            return Err(AudioLibraryError::Invalid(WavError::InvalidFormat(
+                "No audio data".to_string(),
+            )));
+        }
+
+        let bytes = wav.to_wav_bytes();
+        let id = hex::encode(Sha256::digest(&bytes));
+        let key = Self::key(tenant_id, &id);
+
+        if let Some(existing) = self.files.lock().unwrap().get(&key) {
+            return Ok(existing.clone());
+        }
+
+        let dir = self.directory(tenant_id, category);
+        std::fs::create_dir_all(&dir).map_err(|e| AudioLibraryError::Io(e.to_string()))?;
+        let path = dir.join(format!("{}.wav", id));
+        std::fs::write(&path, &bytes).map_err(|e| AudioLibraryError::Io(e.to_string()))?;
+
+        let stored = StoredAudio {
+            id,
+            tenant_id: tenant_id.map(|t| t.to_string()),
+            name: name.to_string(),
+            category,
+            path,
+            duration: wav.duration(),
+            size: bytes.len() as u64,
+            sample_rate: wav.format.sample_rate,
+            channels: wav.format.channels,
+            uploaded_at: Utc::now(),
+        };
+
+        self.files.lock().unwrap().insert(key, stored.clone());
+        self.save_index()?;
+        Ok(stored)
+    }
+
+    /// Get a stored file
+    pub fn get(&self, tenant_id: Option<&str>, id: &str) -> Option<StoredAudio> {
+        self.files.lock().unwrap().get(&Self::key(tenant_id, id)).cloned()
+    }
+
+    /// List a tenant's files, optionally filtered by category, newest first
+    pub fn list(&self, tenant_id: Option<&str>, category: Option<AudioCategory>) -> Vec<StoredAudio> {
+        let tenant = tenant_id.map(|t| t.to_string());
+        let mut files: Vec<StoredAudio> = self
+            .files
+            .lock()
+            .unwrap()
+            .values()
+            .filter(|f| f.tenant_id == tenant)
+            .filter(|f| category.map(|c| f.category == c).unwrap_or(true))
+            .cloned()
+            .collect();
+        files.sort_by_key(|f| std::cmp::Reverse(f.uploaded_at));
+        files
+    }
+
+    /// Record that `reference` uses a file
+    pub fn add_reference(&self, tenant_id: Option<&str>, id: &str, reference: AudioReference) {
+        let mut references = self.references.lock().unwrap();
+        let refs = references.entry(Self::key(tenant_id, id)).or_default();
+        if !refs.contains(&reference) {
+            refs.push(reference);
+        }
+    }
+
+    /// Remove a reference previously added
+    pub fn remove_reference(&self, tenant_id: Option<&str>, id: &str, reference: &AudioReference) {
+        let mut references = self.references.lock().unwrap();
+        let key = Self::key(tenant_id, id);
+        if let Some(refs) = references.get_mut(&key) {
+            refs.retain(|r| r != reference);
+            if refs.is_empty() {
+                references.remove(&key);
+            }
+        }
+    }
+
+    /// Users of a file
+    pub fn references(&self, tenant_id: Option<&str>, id: &str) -> Vec<AudioReference> {
+        self.references
+            .lock()
+            .unwrap()
+            .get(&Self::key(tenant_id, id))
+            .cloned()
+            .unwrap_or_default()
+    }
+
+    /// Delete a file unless it is still referenced
+    pub fn delete(&self, tenant_id: Option<&str>, id: &str) -> Result<StoredAudio, AudioLibraryError> {
+        let key = Self::key(tenant_id, id);
+
+        let refs = self.references(tenant_id, id);
+        if !refs.is_empty() {
+            return Err(AudioLibraryError::InUse(refs));
+        }
+
+        let stored = self
+            .files
+            .lock()
+            .unwrap()
+            .remove(&key)
+            .ok_or_else(|| AudioLibraryError::NotFound(id.to_string()))?;
+
+        if let Err(e) = std::fs::remove_file(&stored.path) {
+            if e.kind() != std::io::ErrorKind::NotFound {
+                return Err(AudioLibraryError::Io(e.to_string()));
+            }
+        }
+        self.pcm_cache.lock().unwrap().remove(&stored.path.to_string_lossy());
+        self.save_index()?;
+
+        Ok(stored)
+    }
+
+    /// Decoded 16-bit PCM samples of a file, cached with an LRU bound
+    pub fn pcm(&self, tenant_id: Option<&str>, id: &str) -> Result<Arc<Vec<i16>>, AudioLibraryError> {
+        let stored = self
+            .get(tenant_id, id)
+            .ok_or_else(|| AudioLibraryError::NotFound(id.to_string()))?;
+        self.pcm_for_path(&stored.path)
+    }
+
+    fn pcm_for_path(&self, path: &Path) -> Result<Arc<Vec<i16>>, AudioLibraryError> {
+        let cache_key = path.to_string_lossy().to_string();
+        if let Some(samples) = self.pcm_cache.lock().unwrap().get(&cache_key) {
+            return Ok(samples);
+        }
+
+        // Files stored before conversion was enabled may be in any format
+        let samples = TelephonyConverter::open(path, NARROWBAND_RATE)
+            .and_then(TelephonyConverter::read_to_end)
+            .map_err(AudioLibraryError::Invalid)?;
+        let samples = Arc::new(samples);
+        self.pcm_cache
+            .lock()
+            .unwrap()
+            .insert(cache_key, samples.clone());
+        Ok(samples)
+    }
+}
+
+#[async_trait]
+impl Lint for AudioLibrary {
+    fn source(&self) -> &'static str {
+        "audio_library"
+    }
+
+    async fn inventory(&self, inventory: &mut RoutingInventory) {
+        inventory.provide(EntityKind::AudioFile);
+        for (tenant, id) in self.files.lock().unwrap().keys() {
+            inventory.known(EntityKind::AudioFile, tenant.as_deref(), id);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::domain::audio::wav::WavFormat;
+
+    fn temp_root(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("yakyak-audio-{}-{}", name, uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn stereo_44k_wav(seconds: f64) -> Vec<u8> {
+        let frames = (44100.0 * seconds) as usize;
+        let mut data = Vec::with_capacity(frames * 4);
+        for i in 0..frames {
+            let sample = ((i as f64 * 440.0 * 2.0 * std::f64::consts::PI / 44100.0).sin() * 8000.0) as i16;
+            data.extend_from_slice(&sample.to_le_bytes());
+            data.extend_from_slice(&sample.to_le_bytes());
+        }
+        WavFile {
+            format: WavFormat {
+                channels: 2,
+                sample_rate: 44100,
+                bits_per_sample: 16,
+                audio_format: 1,
+            },
+            data: Arc::new(data),
+        }
+        .to_wav_bytes()
+    }
+
+    fn library(name: &str) -> AudioLibrary {
+        AudioLibrary::new(AudioLibraryConfig {
+            root: temp_root(name),
+            ..Default::default()
+        })
+    }
+
+    #[test]
+    fn test_upload_converts_to_8k_mono() {
+        let library = library("convert");
+        let stored = library
+            .upload(Some("acme"), "hold-music", AudioCategory::Moh, &stereo_44k_wav(0.5))
+            .unwrap();
+
+        assert_eq!(stored.sample_rate, 8000);
+        assert_eq!(stored.channels, 1);
+        assert!((stored.duration - 0.5).abs() < 0.01);
+        assert!(stored.path.starts_with(library.config().root.join("acme").join("moh")));
+
+        let on_disk = WavFile::from_file(&stored.path).unwrap();
+        assert_eq!(on_disk.format.sample_rate, 8000);
+        assert_eq!(on_disk.format.channels, 1);
+
+        let samples = library.pcm(Some("acme"), &stored.id).unwrap();
+        assert_eq!(samples.len(), 4000);
+    }
+
+    #[test]
+    fn test_upload_rejected_without_auto_convert() {
+        let library = AudioLibrary::new(AudioLibraryConfig {
+            root: temp_root("strict"),
+            auto_convert: false,
+            ..Default::default()
+        });
+
+        assert_eq!(
+            library
+                .upload(None, "hold", AudioCategory::Moh, &stereo_44k_wav(0.1))
+                .unwrap_err(),
+            AudioLibraryError::UnsupportedFormat {
+                sample_rate: 44100,
+                channels: 2
+            }
+        );
+        assert!(matches!(
+            library.upload(None, "junk", AudioCategory::Prompt, b"not a wav file"),
+            Err(AudioLibraryError::Invalid(_))
+        ));
+    }
+
+    #[test]
+    fn test_identical_uploads_dedupe() {
+        let library = library("dedupe");
+        let wav = stereo_44k_wav(0.1);
+
+        let first = library.upload(None, "a", AudioCategory::Prompt, &wav).unwrap();
+        let second = library.upload(None, "b", AudioCategory::Prompt, &wav).unwrap();
+
+        assert_eq!(first.id, second.id);
+        assert_eq!(library.list(None, None).len(), 1);
+        // Tenants are isolated
+        assert!(library.list(Some("acme"), None).is_empty());
+    }
+
+    #[test]
+    fn test_delete_protected_by_references() {
+        let library = library("delete");
+        let stored = library
+            .upload(Some("acme"), "greeting", AudioCategory::Prompt, &stereo_44k_wav(0.1))
+            .unwrap();
+
+        let ivr = AudioReference::new("ivr", "main-menu");
+        let greeting = AudioReference::new("voicemail_greeting", "alice");
+        library.add_reference(Some("acme"), &stored.id, ivr.clone());
+        library.add_reference(Some("acme"), &stored.id, greeting.clone());
+
+        match library.delete(Some("acme"), &stored.id) {
+            Err(AudioLibraryError::InUse(refs)) => {
+                assert_eq!(refs, vec![ivr.clone(), greeting.clone()]);
+            }
+            other => panic!("expected InUse, got {:?}", other),
+        }
+        assert!(stored.path.exists());
+
+        library.remove_reference(Some("acme"), &stored.id, &ivr);
+        library.remove_reference(Some("acme"), &stored.id, &greeting);
+        library.delete(Some("acme"), &stored.id).unwrap();
+
+        assert!(!stored.path.exists());
+        assert!(library.get(Some("acme"), &stored.id).is_none());
+    }
+
+    #[test]
+    fn test_index_survives_restart() {
+        let root = temp_root("index");
+        let config = AudioLibraryConfig {
+            root: root.clone(),
+            ..Default::default()
+        };
+
+        let stored = AudioLibrary::new(config.clone())
+            .upload(None, "welcome", AudioCategory::Announcement, &stereo_44k_wav(0.1))
+            .unwrap();
+
+        let reopened = AudioLibrary::new(config);
+        let loaded = reopened.get(None, &stored.id).unwrap();
+        assert_eq!(loaded.name, "welcome");
+        assert_eq!(loaded.path, stored.path);
+    }
+
+    #[test]
+    fn test_pcm_cache_is_bounded() {
+        let mut cache = PcmCache::new(2);
+        cache.insert("a".to_string(), Arc::new(vec![1]));
+        cache.insert("b".to_string(), Arc::new(vec![2]));
+        assert!(cache.get("a").is_some());
+        cache.insert("c".to_string(), Arc::new(vec![3]));
+
+        // "b" was least recently used
+        assert!(cache.get("b").is_none());
+        assert!(cache.get("a").is_some());
+        assert!(cache.get("c").is_some());
+    }
+}
@@ -1,5 +1,5 @@
 /// Audio file management and organization
-use crate::domain::audio::wav::{WavFile, WavError};
+use crate::domain::audio::wav::{WavError, WavFile, NARROWBAND_RATE};
 use std::collections::HashMap;
 use std::path::{Path, PathBuf};
 use std::sync::Arc;
@@ -157,8 +157,8 @@ impl AudioFileManager {
             self.base_dir.join(path)
         };
 
-        // Load the WAV file
-        let wav_file = WavFile::from_file(&file_path)?;
+        // Load the WAV file, converted for playback into calls
+        let wav_file = WavFile::from_file_converted(&file_path, NARROWBAND_RATE)?;
         let duration = wav_file.duration();
 
         // Get file size
@@ -3,9 +3,16 @@
 pub mod wav;
 pub mod player;
 pub mod manager;
+pub mod library;
 pub mod sequence;
+pub mod digits;
 
-pub use wav::{WavFile, WavFormat, WavError};
+pub use wav::{
+    Resampler, TelephonyConverter, WavError, WavFile, WavFormat, WavReader, NARROWBAND_RATE,
+    WIDEBAND_RATE,
+};
 pub use player::{AudioPlayer, AudioPlayerState, PlaybackOptions, StreamingAudioPlayer};
 pub use manager::{AudioFileManager, AudioFileInfo, Language};
+pub use library::{AudioCategory, AudioLibrary, AudioLibraryConfig, AudioLibraryError, AudioReference, StoredAudio};
 pub use sequence::{SequentialPlayer, SequenceBuilder};
+pub use digits::{concat_prompts, digit_prompts, time_prompts};
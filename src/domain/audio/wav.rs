@@ -1,23 +1,94 @@
-/// WAV file parsing and audio data extraction
+//! WAV file parsing, decoding and conversion to telephony audio
+//!
+//! Prompts arrive in whatever format the tool that made them wrote: 44.1 kHz
+//! stereo, 24-bit, floating point, `WAVE_FORMAT_EXTENSIBLE`. [`WavReader`]
+//! decodes integer PCM (8/16/24/32-bit) and IEEE float data block by block,
+//! and [`TelephonyConverter`] downmixes it to mono and resamples it to the
+//! 8 or 16 kHz of the telephony codecs as it reads, so a large file is never
+//! decoded in one piece. Compressed encodings (GSM 6.10, ADPCM, ...) are
+//! refused with [`WavError::UnsupportedEncoding`] naming the encoding.
+
 use std::fs::File;
-use std::io::{self, Read, Seek, SeekFrom};
+use std::io::{self, BufReader, Read};
 use std::path::Path;
 use std::sync::Arc;
 
+/// Integer PCM samples
+pub const WAVE_FORMAT_PCM: u16 = 0x0001;
+/// 32- or 64-bit floating point samples
+pub const WAVE_FORMAT_IEEE_FLOAT: u16 = 0x0003;
+/// Format given by the sub-format GUID of an extended fmt chunk
+pub const WAVE_FORMAT_EXTENSIBLE: u16 = 0xFFFE;
+
+/// Sample rate of G.711
+pub const NARROWBAND_RATE: u32 = 8000;
+/// Sample rate of G.722
+pub const WIDEBAND_RATE: u32 = 16000;
+
+/// Most channels downmixed to mono
+const MAX_CHANNELS: u16 = 8;
+
+/// Largest fmt chunk read (extended ones are 40 bytes)
+const MAX_FMT_CHUNK: usize = 1024;
+
+/// Bytes 2..16 of the sub-format GUID of extensible files, whose first two
+/// bytes are the format code (`XXXX0000-0000-0010-8000-00AA00389B71`)
+const SUBFORMAT_GUID_TAIL: [u8; 14] = [
+    0x00, 0x00, 0x00, 0x00, 0x10, 0x00, 0x80, 0x00, 0x00, 0xAA, 0x00, 0x38, 0x9B, 0x71,
+];
+
 /// WAV file format errors
 #[derive(Debug, Clone, PartialEq)]
 pub enum WavError {
     IoError(String),
     InvalidFormat(String),
     UnsupportedFormat(String),
+    /// Compressed encoding that would have to be decoded first
+    UnsupportedEncoding { format_tag: u16, name: String },
+}
+
+impl std::fmt::Display for WavError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            WavError::IoError(e) => write!(f, "I/O error: {}", e),
+            WavError::InvalidFormat(e) | WavError::UnsupportedFormat(e) => write!(f, "{}", e),
+            WavError::UnsupportedEncoding { name, .. } => write!(
+                f,
+                "{} encoded WAV files are not supported; convert to PCM (16-bit) WAV",
+                name
+            ),
+        }
+    }
 }
 
+impl std::error::Error for WavError {}
+
 impl From<io::Error> for WavError {
     fn from(err: io::Error) -> Self {
         WavError::IoError(err.to_string())
     }
 }
 
+/// Name of a WAV format code users may recognise
+fn encoding_name(format_tag: u16) -> String {
+    let name = match format_tag {
+        0x0002 => "Microsoft ADPCM",
+        0x0006 => "G.711 A-law",
+        0x0007 => "G.711 mu-law",
+        0x0011 => "IMA ADPCM",
+        0x0031 => "GSM 6.10",
+        0x0040 => "G.721 ADPCM",
+        0x0050 => "MPEG",
+        0x0055 => "MP3",
+        0x0064 => "G.726 ADPCM",
+        0x0065 => "G.722 ADPCM",
+        0x0161 => "Windows Media Audio",
+        0x1610 => "AAC",
+        _ => return format!("Format 0x{:04X}", format_tag),
+    };
+    name.to_string()
+}
+
 /// WAV audio format
 #[derive(Debug, Clone, PartialEq)]
 pub struct WavFormat {
@@ -25,9 +96,10 @@ pub struct WavFormat {
     pub channels: u16,
     /// Sample rate in Hz (e.g., 8000, 16000, 44100, 48000)
     pub sample_rate: u32,
-    /// Bits per sample (8, 16, 24, 32)
+    /// Bits per sample (8, 16, 24, 32; 32 or 64 for float)
     pub bits_per_sample: u16,
-    /// Audio format code (1 = PCM)
+    /// Sample encoding: 1 = integer PCM, 3 = IEEE float (for extensible
+    /// files, the code of their sub-format)
     pub audio_format: u16,
 }
 
@@ -42,6 +114,11 @@ impl WavFormat {
         self.sample_rate != 8000
     }
 
+    /// Whether samples are IEEE floats
+    pub fn is_float(&self) -> bool {
+        self.audio_format == WAVE_FORMAT_IEEE_FLOAT
+    }
+
     /// Get bytes per sample
     pub fn bytes_per_sample(&self) -> usize {
         (self.bits_per_sample / 8) as usize
@@ -57,26 +134,161 @@ impl WavFormat {
         let frames = data_size / self.bytes_per_frame();
         frames as f64 / self.sample_rate as f64
     }
+
+    /// Mono 16-bit PCM at `sample_rate`, what the converter produces
+    pub fn telephony(sample_rate: u32) -> Self {
+        Self {
+            channels: 1,
+            sample_rate,
+            bits_per_sample: 16,
+            audio_format: WAVE_FORMAT_PCM,
+        }
+    }
+
+    /// Parse a fmt chunk, resolving extensible formats to their sub-format
+    fn parse(fmt_data: &[u8]) -> Result<Self, WavError> {
+        if fmt_data.len() < 16 {
+            return Err(WavError::InvalidFormat("fmt chunk too small".to_string()));
+        }
+
+        let u16_at = |i: usize| u16::from_le_bytes([fmt_data[i], fmt_data[i + 1]]);
+        let mut audio_format = u16_at(0);
+        let channels = u16_at(2);
+        let sample_rate =
+            u32::from_le_bytes([fmt_data[4], fmt_data[5], fmt_data[6], fmt_data[7]]);
+        // Bytes 8..14 are the byte rate and block alignment, derived below
+        let bits_per_sample = u16_at(14);
+
+        if audio_format == WAVE_FORMAT_EXTENSIBLE {
+            // cbSize, valid bits and channel mask precede the sub-format
+            if fmt_data.len() < 40 {
+                return Err(WavError::InvalidFormat(
+                    "Extensible fmt chunk too small".to_string(),
+                ));
+            }
+            let sub_format = &fmt_data[24..40];
+            if sub_format[2..] != SUBFORMAT_GUID_TAIL {
+                return Err(WavError::UnsupportedEncoding {
+                    format_tag: WAVE_FORMAT_EXTENSIBLE,
+                    name: "Unknown extensible sub-format".to_string(),
+                });
+            }
+            audio_format = u16_at(24);
+        }
+
+        let bits_supported = match audio_format {
+            WAVE_FORMAT_PCM => matches!(bits_per_sample, 8 | 16 | 24 | 32),
+            WAVE_FORMAT_IEEE_FLOAT => matches!(bits_per_sample, 32 | 64),
+            other => {
+                return Err(WavError::UnsupportedEncoding {
+                    format_tag: other,
+                    name: encoding_name(other),
+                })
+            }
+        };
+
+        if channels == 0 || channels > MAX_CHANNELS {
+            return Err(WavError::InvalidFormat(format!(
+                "Invalid number of channels: {}",
+                channels
+            )));
+        }
+
+        if sample_rate == 0 {
+            return Err(WavError::InvalidFormat(
+                "Invalid sample rate: 0".to_string(),
+            ));
+        }
+
+        if !bits_supported {
+            return Err(WavError::UnsupportedFormat(format!(
+                "Unsupported bits per sample: {}",
+                bits_per_sample
+            )));
+        }
+
+        Ok(WavFormat {
+            channels,
+            sample_rate,
+            bits_per_sample,
+            audio_format,
+        })
+    }
+
+    /// Decode one sample to [-1.0, 1.0]
+    fn decode_sample(&self, bytes: &[u8]) -> f32 {
+        match (self.is_float(), self.bits_per_sample) {
+            (true, 32) => f32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]),
+            (true, 64) => {
+                let mut raw = [0u8; 8];
+                raw.copy_from_slice(&bytes[..8]);
+                f64::from_le_bytes(raw) as f32
+            }
+            (false, 8) => (bytes[0] as f32 - 128.0) / 128.0,
+            (false, 16) => i16::from_le_bytes([bytes[0], bytes[1]]) as f32 / 32768.0,
+            (false, 24) => {
+                (i32::from_le_bytes([0, bytes[0], bytes[1], bytes[2]]) >> 8) as f32 / 8_388_608.0
+            }
+            (false, 32) => {
+                i32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]) as f32
+                    / 2_147_483_648.0
+            }
+            _ => 0.0,
+        }
+    }
+
+    /// Decode whole frames of `data`, appending interleaved samples
+    fn decode(&self, data: &[u8], out: &mut Vec<f32>) {
+        let size = self.bytes_per_sample();
+        let whole = data.len() - data.len() % self.bytes_per_frame();
+        out.extend(data[..whole].chunks_exact(size).map(|s| self.decode_sample(s)));
+    }
 }
 
-/// WAV file representation
-#[derive(Debug, Clone)]
-pub struct WavFile {
-    /// Audio format information
-    pub format: WavFormat,
-    /// Raw audio data (PCM samples)
-    pub data: Arc<Vec<u8>>,
+/// A sample in [-1.0, 1.0] as 16-bit PCM
+fn to_i16(sample: f32) -> i16 {
+    (sample * 32768.0).round().clamp(-32768.0, 32767.0) as i16
 }
 
-impl WavFile {
-    /// Load WAV file from path
-    pub fn from_file<P: AsRef<Path>>(path: P) -> Result<Self, WavError> {
-        let mut file = File::open(path)?;
-        Self::from_reader(&mut file)
+/// Read until `buf` is full or the reader is at its end
+fn read_full<R: Read>(reader: &mut R, buf: &mut [u8]) -> io::Result<usize> {
+    let mut filled = 0;
+    while filled < buf.len() {
+        match reader.read(&mut buf[filled..]) {
+            Ok(0) => break,
+            Ok(n) => filled += n,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+            Err(e) => return Err(e),
+        }
     }
+    Ok(filled)
+}
 
-    /// Parse WAV file from reader
-    pub fn from_reader<R: Read + Seek>(reader: &mut R) -> Result<Self, WavError> {
+/// Streaming WAV decoder
+///
+/// Reads the header up to the data chunk, then decodes the data a block at
+/// a time. The fmt chunk must come before the data chunk, as every common
+/// writer puts it.
+pub struct WavReader<R> {
+    reader: R,
+    format: WavFormat,
+    /// Bytes of the data chunk not read yet
+    remaining: u64,
+    /// Size of the data chunk as declared
+    data_len: u64,
+    buffer: Vec<u8>,
+}
+
+impl WavReader<BufReader<File>> {
+    /// Open a WAV file
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self, WavError> {
+        Self::new(BufReader::new(File::open(path)?))
+    }
+}
+
+impl<R: Read> WavReader<R> {
+    /// Read the header of a WAV stream
+    pub fn new(mut reader: R) -> Result<Self, WavError> {
         // Read RIFF header
         let mut riff_header = [0u8; 12];
         reader.read_exact(&mut riff_header)?;
@@ -95,20 +307,11 @@ impl WavFile {
             ));
         }
 
-        let _file_size = u32::from_le_bytes([
-            riff_header[4],
-            riff_header[5],
-            riff_header[6],
-            riff_header[7],
-        ]);
-
-        // Parse chunks until we find fmt and data
+        // Parse chunks until the data chunk
         let mut format: Option<WavFormat> = None;
-        let mut data: Option<Vec<u8>> = None;
-
         loop {
             let mut chunk_header = [0u8; 8];
-            if reader.read_exact(&mut chunk_header).is_err() {
+            if read_full(&mut reader, &mut chunk_header)? < chunk_header.len() {
                 break; // End of file
             }
 
@@ -118,116 +321,411 @@ impl WavFile {
                 chunk_header[5],
                 chunk_header[6],
                 chunk_header[7],
-            ]) as usize;
+            ]) as u64;
+            // WAV chunks are word-aligned
+            let padding = chunk_size % 2;
 
             match chunk_id {
                 b"fmt " => {
-                    format = Some(Self::parse_fmt_chunk(reader, chunk_size)?);
+                    if chunk_size as usize > MAX_FMT_CHUNK {
+                        return Err(WavError::InvalidFormat(
+                            "fmt chunk too large".to_string(),
+                        ));
+                    }
+                    let mut fmt_data = vec![0u8; chunk_size as usize];
+                    reader.read_exact(&mut fmt_data)?;
+                    format = Some(WavFormat::parse(&fmt_data)?);
+                    Self::skip(&mut reader, padding)?;
                 }
                 b"data" => {
-                    let mut audio_data = vec![0u8; chunk_size];
-                    reader.read_exact(&mut audio_data)?;
-                    data = Some(audio_data);
+                    let format = format.ok_or_else(|| {
+                        WavError::InvalidFormat("data chunk before fmt chunk".to_string())
+                    })?;
+                    return Ok(Self {
+                        reader,
+                        format,
+                        remaining: chunk_size,
+                        data_len: chunk_size,
+                        buffer: Vec::new(),
+                    });
                 }
                 _ => {
                     // Skip unknown chunks
-                    reader.seek(SeekFrom::Current(chunk_size as i64))?;
+                    Self::skip(&mut reader, chunk_size + padding)?;
                 }
             }
+        }
 
-            // Align to even byte boundary (WAV chunks are word-aligned)
-            if chunk_size % 2 != 0 {
-                reader.seek(SeekFrom::Current(1))?;
-            }
+        Err(match format {
+            None => WavError::InvalidFormat("Missing fmt chunk".to_string()),
+            Some(_) => WavError::InvalidFormat("Missing data chunk".to_string()),
+        })
+    }
+
+    fn skip(reader: &mut R, len: u64) -> io::Result<()> {
+        io::copy(&mut reader.by_ref().take(len), &mut io::sink())?;
+        Ok(())
+    }
+
+    /// Format of the data
+    pub fn format(&self) -> &WavFormat {
+        &self.format
+    }
+
+    /// Duration in seconds, from the declared size of the data chunk
+    pub fn duration(&self) -> f64 {
+        self.format.calculate_duration(self.data_len as usize)
+    }
+
+    /// Read up to `max_frames` frames, appending them to `out` as
+    /// interleaved samples in [-1.0, 1.0]
+    ///
+    /// Returns the number of frames read; 0 at the end of the data.
+    pub fn read_frames(&mut self, max_frames: usize, out: &mut Vec<f32>) -> Result<usize, WavError> {
+        let frame_size = self.format.bytes_per_frame();
+        let wanted = (max_frames as u64 * frame_size as u64).min(self.remaining) as usize;
+        self.buffer.resize(wanted, 0);
+        let read = read_full(&mut self.reader, &mut self.buffer)?;
+        // A short read is the end of a truncated file
+        self.remaining = if read < wanted { 0 } else { self.remaining - read as u64 };
+
+        let frames = read / frame_size;
+        self.format.decode(&self.buffer[..frames * frame_size], out);
+        Ok(frames)
+    }
+
+    /// Like `read_frames`, averaging the channels of every frame
+    pub fn read_mono(&mut self, max_frames: usize, out: &mut Vec<f32>) -> Result<usize, WavError> {
+        let channels = self.format.channels as usize;
+        if channels == 1 {
+            return self.read_frames(max_frames, out);
+        }
+        let mut interleaved = Vec::with_capacity(max_frames * channels);
+        let frames = self.read_frames(max_frames, &mut interleaved)?;
+        out.extend(
+            interleaved
+                .chunks_exact(channels)
+                .map(|frame| frame.iter().sum::<f32>() / channels as f32),
+        );
+        Ok(frames)
+    }
+
+    /// The rest of the data chunk, undecoded
+    pub fn read_data(self) -> Result<Vec<u8>, WavError> {
+        let mut data = Vec::new();
+        self.reader.take(self.remaining).read_to_end(&mut data)?;
+        let whole = data.len() - data.len() % self.format.bytes_per_frame();
+        data.truncate(whole);
+        Ok(data)
+    }
+}
+
+/// Filter zero crossings on each side of an output sample
+const ZERO_CROSSINGS: f64 = 16.0;
+
+/// Cutoff as a fraction of the lower Nyquist frequency, leaving room for
+/// the filter's transition band
+const ROLLOFF: f64 = 0.94;
+
+/// Most precomputed filter phases; finer positions use the nearest one
+const MAX_PHASES: u64 = 1024;
+
+/// Band-limited sample rate converter for one channel, fed in blocks
+///
+/// With the rates reduced to `from = M * g` and `to = L * g`, output sample
+/// `k` lies at input position `k * M / L`. Integer ratios (48 or 16 kHz to
+/// 8 kHz, where `L` is 1) use a single windowed-sinc filter; other ratios
+/// (44.1 kHz to 8 kHz: `L` = 80) use one filter phase per fractional
+/// position. The output is `ceil(n * L / M)` samples for `n` input samples.
+pub struct Resampler {
+    up: u64,
+    down: u64,
+    /// Taps on each side of an output position, in input samples
+    half_width: usize,
+    /// Taps per fractional position, `2 * half_width` each
+    phases: Vec<Vec<f32>>,
+    /// Input still needed, starting at input sample `base`
+    input: Vec<f32>,
+    base: u64,
+    received: u64,
+    produced: u64,
+}
+
+impl Resampler {
+    pub fn new(from_rate: u32, to_rate: u32) -> Self {
+        let (from, to) = (from_rate.max(1) as u64, to_rate.max(1) as u64);
+        let g = gcd(from, to);
+        let (up, down) = (to / g, from / g);
+
+        // Cycles per input sample: below the output's Nyquist frequency
+        // when downsampling, the input's when upsampling
+        let cutoff = 0.5 * ROLLOFF * (up as f64 / down as f64).min(1.0);
+        let half_width = (ZERO_CROSSINGS / (2.0 * cutoff)).ceil() as usize;
+        let phase_count = up.min(MAX_PHASES);
+        let phases = (0..phase_count)
+            .map(|phase| {
+                let frac = phase as f64 / phase_count as f64;
+                let mut taps: Vec<f64> = (0..2 * half_width)
+                    .map(|j| {
+                        // Distance from the output position to tap j
+                        let x = frac + half_width as f64 - 1.0 - j as f64;
+                        2.0 * cutoff * sinc(2.0 * cutoff * x) * blackman(x / half_width as f64)
+                    })
+                    .collect();
+                // Unity gain at DC for every phase
+                let sum: f64 = taps.iter().sum();
+                taps.iter_mut().for_each(|t| *t /= sum);
+                taps.into_iter().map(|t| t as f32).collect()
+            })
+            .collect();
+
+        Self {
+            up,
+            down,
+            half_width,
+            phases,
+            input: Vec::new(),
+            base: 0,
+            received: 0,
+            produced: 0,
+        }
+    }
+
+    /// Resample the next block of input, appending what can be computed
+    pub fn process(&mut self, input: &[f32], out: &mut Vec<f32>) {
+        self.input.extend_from_slice(input);
+        self.received += input.len() as u64;
+        self.drain(out, false);
+    }
+
+    /// Output the rest after the last block
+    pub fn finish(&mut self, out: &mut Vec<f32>) {
+        self.drain(out, true);
+    }
 
-            // If we have both format and data, we're done
-            if format.is_some() && data.is_some() {
+    fn drain(&mut self, out: &mut Vec<f32>, flush: bool) {
+        let half = self.half_width as u64;
+        let total = (self.received * self.up).div_ceil(self.down);
+        while self.produced < total {
+            let position = self.produced * self.down;
+            let center = position / self.up;
+            // Past the end the input is zero, known only when flushing
+            if !flush && center + half >= self.received {
                 break;
             }
+            let phase = ((position % self.up) * self.phases.len() as u64 / self.up) as usize;
+            let first = center as i64 + 1 - half as i64;
+            let sample: f32 = self.phases[phase]
+                .iter()
+                .enumerate()
+                .map(|(j, tap)| tap * self.sample(first + j as i64))
+                .sum();
+            out.push(sample);
+            self.produced += 1;
         }
 
-        let format = format.ok_or_else(|| {
-            WavError::InvalidFormat("Missing fmt chunk".to_string())
-        })?;
+        // Keep the input the next output sample needs
+        let next_first = ((self.produced * self.down / self.up) + 1).saturating_sub(half);
+        let drop = next_first.min(self.received).saturating_sub(self.base) as usize;
+        self.input.drain(..drop.min(self.input.len()));
+        self.base += drop as u64;
+    }
 
-        let data = data.ok_or_else(|| {
-            WavError::InvalidFormat("Missing data chunk".to_string())
-        })?;
+    fn sample(&self, index: i64) -> f32 {
+        if index < self.base as i64 {
+            return 0.0;
+        }
+        self.input
+            .get((index - self.base as i64) as usize)
+            .copied()
+            .unwrap_or(0.0)
+    }
+}
 
-        Ok(WavFile {
-            format,
-            data: Arc::new(data),
-        })
+fn gcd(mut a: u64, mut b: u64) -> u64 {
+    while b != 0 {
+        (a, b) = (b, a % b);
+    }
+    a
+}
+
+fn sinc(x: f64) -> f64 {
+    if x.abs() < 1e-12 {
+        1.0
+    } else {
+        let px = std::f64::consts::PI * x;
+        px.sin() / px
     }
+}
+
+/// Blackman window over [-1, 1]
+fn blackman(u: f64) -> f64 {
+    if u.abs() >= 1.0 {
+        return 0.0;
+    }
+    let pu = std::f64::consts::PI * u;
+    0.42 + 0.5 * pu.cos() + 0.08 * (2.0 * pu).cos()
+}
+
+/// Decodes a WAV stream to mono 16-bit PCM at a telephony rate as it reads
+pub struct TelephonyConverter<R> {
+    source: WavReader<R>,
+    /// `None` when the source is at the target rate
+    resampler: Option<Resampler>,
+    target_rate: u32,
+    finished: bool,
+}
+
+impl TelephonyConverter<BufReader<File>> {
+    /// Convert a WAV file
+    pub fn open<P: AsRef<Path>>(path: P, target_rate: u32) -> Result<Self, WavError> {
+        Self::new(WavReader::open(path)?, target_rate)
+    }
+}
+
+impl<R: Read> TelephonyConverter<R> {
+    /// Source frames decoded per block
+    pub const BLOCK_FRAMES: usize = 4096;
 
-    /// Parse fmt chunk
-    fn parse_fmt_chunk<R: Read>(
-        reader: &mut R,
-        chunk_size: usize,
-    ) -> Result<WavFormat, WavError> {
-        if chunk_size < 16 {
+    pub fn new(source: WavReader<R>, target_rate: u32) -> Result<Self, WavError> {
+        if target_rate == 0 {
             return Err(WavError::InvalidFormat(
-                "fmt chunk too small".to_string(),
+                "Invalid target sample rate: 0".to_string(),
             ));
         }
+        let source_rate = source.format().sample_rate;
+        let resampler =
+            (source_rate != target_rate).then(|| Resampler::new(source_rate, target_rate));
+        Ok(Self {
+            source,
+            resampler,
+            target_rate,
+            finished: false,
+        })
+    }
 
-        let mut fmt_data = vec![0u8; chunk_size];
-        reader.read_exact(&mut fmt_data)?;
-
-        let audio_format = u16::from_le_bytes([fmt_data[0], fmt_data[1]]);
-        let channels = u16::from_le_bytes([fmt_data[2], fmt_data[3]]);
-        let sample_rate = u32::from_le_bytes([
-            fmt_data[4],
-            fmt_data[5],
-            fmt_data[6],
-            fmt_data[7],
-        ]);
-        let _byte_rate = u32::from_le_bytes([
-            fmt_data[8],
-            fmt_data[9],
-            fmt_data[10],
-            fmt_data[11],
-        ]);
-        let _block_align = u16::from_le_bytes([fmt_data[12], fmt_data[13]]);
-        let bits_per_sample = u16::from_le_bytes([fmt_data[14], fmt_data[15]]);
-
-        // Only support PCM format
-        if audio_format != 1 {
-            return Err(WavError::UnsupportedFormat(format!(
-                "Only PCM format (1) is supported, got {}",
-                audio_format
-            )));
-        }
+    /// Format of the converted samples
+    pub fn format(&self) -> WavFormat {
+        WavFormat::telephony(self.target_rate)
+    }
 
-        // Validate parameters
-        if channels == 0 || channels > 2 {
-            return Err(WavError::InvalidFormat(format!(
-                "Invalid number of channels: {}",
-                channels
-            )));
-        }
+    /// Format of the source
+    pub fn source_format(&self) -> &WavFormat {
+        self.source.format()
+    }
 
-        if sample_rate == 0 {
-            return Err(WavError::InvalidFormat(
-                "Invalid sample rate: 0".to_string(),
-            ));
+    /// The next block of converted samples; `None` after the last
+    pub fn next_block(&mut self) -> Result<Option<Vec<i16>>, WavError> {
+        let mut mono = Vec::with_capacity(Self::BLOCK_FRAMES);
+        while !self.finished {
+            mono.clear();
+            let frames = self.source.read_mono(Self::BLOCK_FRAMES, &mut mono)?;
+            self.finished = frames == 0;
+
+            let block: Vec<i16> = match &mut self.resampler {
+                Some(resampler) => {
+                    let mut resampled = Vec::new();
+                    if self.finished {
+                        resampler.finish(&mut resampled);
+                    } else {
+                        resampler.process(&mono, &mut resampled);
+                    }
+                    resampled.into_iter().map(to_i16).collect()
+                }
+                None => mono.iter().copied().map(to_i16).collect(),
+            };
+            if !block.is_empty() {
+                return Ok(Some(block));
+            }
         }
+        Ok(None)
+    }
 
-        if bits_per_sample != 8 && bits_per_sample != 16 && bits_per_sample != 24 && bits_per_sample != 32 {
-            return Err(WavError::UnsupportedFormat(format!(
-                "Unsupported bits per sample: {}",
-                bits_per_sample
-            )));
+    /// All remaining converted samples
+    pub fn read_to_end(mut self) -> Result<Vec<i16>, WavError> {
+        let mut samples = Vec::new();
+        while let Some(block) = self.next_block()? {
+            samples.extend_from_slice(&block);
         }
+        Ok(samples)
+    }
+}
 
-        Ok(WavFormat {
-            channels,
-            sample_rate,
-            bits_per_sample,
-            audio_format,
+/// WAV file representation
+#[derive(Debug, Clone)]
+pub struct WavFile {
+    /// Audio format information
+    pub format: WavFormat,
+    /// Raw audio data (PCM samples)
+    pub data: Arc<Vec<u8>>,
+}
+
+impl WavFile {
+    /// Load WAV file from path
+    pub fn from_file<P: AsRef<Path>>(path: P) -> Result<Self, WavError> {
+        Self::from_wav_reader(WavReader::open(path)?)
+    }
+
+    /// Parse WAV file from reader
+    pub fn from_reader<R: Read>(reader: &mut R) -> Result<Self, WavError> {
+        Self::from_wav_reader(WavReader::new(reader)?)
+    }
+
+    /// The rest of a WAV stream, in its own format
+    pub fn from_wav_reader<R: Read>(reader: WavReader<R>) -> Result<Self, WavError> {
+        let format = reader.format().clone();
+        let data = reader.read_data()?;
+        Ok(WavFile {
+            format,
+            data: Arc::new(data),
         })
     }
 
+    /// Load a WAV file as mono 16-bit PCM at `target_rate`, converting it
+    /// as it is read
+    pub fn from_file_converted<P: AsRef<Path>>(path: P, target_rate: u32) -> Result<Self, WavError> {
+        Self::from_converter(TelephonyConverter::open(path, target_rate)?)
+    }
+
+    /// The rest of a WAV stream as mono 16-bit PCM at `target_rate`
+    pub fn from_reader_converted<R: Read>(
+        reader: WavReader<R>,
+        target_rate: u32,
+    ) -> Result<Self, WavError> {
+        Self::from_converter(TelephonyConverter::new(reader, target_rate)?)
+    }
+
+    fn from_converter<R: Read>(converter: TelephonyConverter<R>) -> Result<Self, WavError> {
+        let format = converter.format();
+        Ok(Self::from_samples(format, &converter.read_to_end()?))
+    }
+
+    /// 16-bit PCM samples in `format`
+    fn from_samples(format: WavFormat, samples: &[i16]) -> Self {
+        let mut data = Vec::with_capacity(samples.len() * 2);
+        for sample in samples {
+            data.extend_from_slice(&sample.to_le_bytes());
+        }
+        WavFile {
+            format,
+            data: Arc::new(data),
+        }
+    }
+
+    /// Mono 16-bit PCM samples at `sample_rate`, e.g. a recording
+    pub fn from_pcm(sample_rate: u32, samples: &[i16]) -> Self {
+        Self::from_samples(WavFormat::telephony(sample_rate), samples)
+    }
+
+    /// `parts` one after the other, as mono 16-bit PCM at `target_rate`
+    pub fn concat(parts: &[WavFile], target_rate: u32) -> Self {
+        let samples: Vec<i16> = parts
+            .iter()
+            .flat_map(|part| part.to_telephony(target_rate).samples_i16())
+            .collect();
+        Self::from_pcm(target_rate, &samples)
+    }
+
     /// Get audio duration in seconds
     pub fn duration(&self) -> f64 {
         self.format.calculate_duration(self.data.len())
@@ -235,132 +733,111 @@ impl WavFile {
 
     /// Get audio data as signed 16-bit samples
     pub fn samples_i16(&self) -> Vec<i16> {
-        match self.format.bits_per_sample {
-            8 => {
-                // 8-bit unsigned to 16-bit signed
-                self.data
-                    .iter()
-                    .map(|&b| ((b as i16) - 128) * 256)
-                    .collect()
-            }
-            16 => {
-                // Already 16-bit signed
-                self.data
-                    .chunks_exact(2)
-                    .map(|chunk| i16::from_le_bytes([chunk[0], chunk[1]]))
-                    .collect()
-            }
-            24 => {
-                // 24-bit to 16-bit (take upper 16 bits)
-                self.data
-                    .chunks_exact(3)
-                    .map(|chunk| i16::from_le_bytes([chunk[1], chunk[2]]))
-                    .collect()
-            }
-            32 => {
-                // 32-bit to 16-bit (take upper 16 bits)
-                self.data
-                    .chunks_exact(4)
-                    .map(|chunk| i16::from_le_bytes([chunk[2], chunk[3]]))
-                    .collect()
-            }
-            _ => Vec::new(),
-        }
+        let mut samples = Vec::with_capacity(self.data.len() / self.format.bytes_per_sample().max(1));
+        self.format.decode(&self.data, &mut samples);
+        samples.into_iter().map(to_i16).collect()
     }
 
-    /// Convert stereo to mono by averaging channels
+    /// Convert to mono by averaging channels
     pub fn to_mono(&self) -> Self {
         if self.format.channels == 1 {
             return self.clone();
         }
 
-        let samples = self.samples_i16();
-        let mono_samples: Vec<i16> = samples
-            .chunks_exact(2)
-            .map(|chunk| {
-                let avg = (chunk[0] as i32 + chunk[1] as i32) / 2;
-                avg as i16
+        let channels = self.format.channels as usize;
+        let mono_samples: Vec<i16> = self
+            .samples_i16()
+            .chunks_exact(channels)
+            .map(|frame| {
+                let sum: i32 = frame.iter().map(|&s| s as i32).sum();
+                (sum / channels as i32) as i16
             })
             .collect();
 
-        // Convert back to bytes
-        let mut mono_data = Vec::with_capacity(mono_samples.len() * 2);
-        for sample in mono_samples {
-            mono_data.extend_from_slice(&sample.to_le_bytes());
-        }
-
-        WavFile {
-            format: WavFormat {
-                channels: 1,
-                bits_per_sample: 16,
-                sample_rate: self.format.sample_rate,
-                audio_format: 1,
-            },
-            data: Arc::new(mono_data),
-        }
+        Self::from_samples(WavFormat::telephony(self.format.sample_rate), &mono_samples)
     }
 
-    /// Simple resampling to target sample rate
-    /// Uses linear interpolation for upsampling and decimation for downsampling
+    /// Resample every channel to `target_rate` (16-bit PCM)
     pub fn resample(&self, target_rate: u32) -> Self {
         if self.format.sample_rate == target_rate {
             return self.clone();
         }
 
+        let channels = self.format.channels as usize;
         let samples = self.samples_i16();
-        let ratio = self.format.sample_rate as f64 / target_rate as f64;
-        let new_length = (samples.len() as f64 / ratio) as usize;
-
-        let resampled: Vec<i16> = (0..new_length)
-            .map(|i| {
-                let src_pos = i as f64 * ratio;
-                let src_idx = src_pos as usize;
-
-                if src_idx + 1 >= samples.len() {
-                    return samples[samples.len() - 1];
-                }
-
-                // Linear interpolation
-                let frac = src_pos - src_idx as f64;
-                let sample1 = samples[src_idx] as f64;
-                let sample2 = samples[src_idx + 1] as f64;
-                let interpolated = sample1 + (sample2 - sample1) * frac;
-                interpolated as i16
-            })
-            .collect();
-
-        // Convert to bytes
-        let mut resampled_data = Vec::with_capacity(resampled.len() * 2);
-        for sample in resampled {
-            resampled_data.extend_from_slice(&sample.to_le_bytes());
+        let mut resampled: Vec<Vec<f32>> = Vec::with_capacity(channels);
+        for channel in 0..channels {
+            let input: Vec<f32> = samples
+                .iter()
+                .skip(channel)
+                .step_by(channels)
+                .map(|&s| s as f32 / 32768.0)
+                .collect();
+            let mut resampler = Resampler::new(self.format.sample_rate, target_rate);
+            let mut output = Vec::new();
+            resampler.process(&input, &mut output);
+            resampler.finish(&mut output);
+            resampled.push(output);
         }
 
-        WavFile {
-            format: WavFormat {
+        let frames = resampled.first().map(Vec::len).unwrap_or(0);
+        let interleaved: Vec<i16> = (0..frames)
+            .flat_map(|i| resampled.iter().map(move |channel| to_i16(channel[i])))
+            .collect();
+        Self::from_samples(
+            WavFormat {
                 sample_rate: target_rate,
                 bits_per_sample: 16,
                 channels: self.format.channels,
-                audio_format: 1,
+                audio_format: WAVE_FORMAT_PCM,
             },
-            data: Arc::new(resampled_data),
-        }
+            &interleaved,
+        )
     }
 
-    /// Convert to G.711 compatible format (8kHz, mono, 16-bit)
-    pub fn to_g711_compatible(&self) -> Self {
-        let mut result = self.clone();
+    /// Encode as a canonical PCM WAV file (RIFF header + fmt + data)
+    pub fn to_wav_bytes(&self) -> Vec<u8> {
+        let data_len = self.data.len() as u32;
+        let block_align = self.format.bytes_per_frame() as u16;
+        let byte_rate = self.format.sample_rate * block_align as u32;
 
-        // Convert to mono if stereo
-        if result.format.channels > 1 {
-            result = result.to_mono();
-        }
+        let mut out = Vec::with_capacity(44 + self.data.len());
+        out.extend_from_slice(b"RIFF");
+        out.extend_from_slice(&(36 + data_len).to_le_bytes());
+        out.extend_from_slice(b"WAVE");
 
-        // Resample to 8kHz if needed
-        if result.format.sample_rate != 8000 {
-            result = result.resample(8000);
-        }
+        out.extend_from_slice(b"fmt ");
+        out.extend_from_slice(&16u32.to_le_bytes());
+        out.extend_from_slice(&self.format.audio_format.to_le_bytes());
+        out.extend_from_slice(&self.format.channels.to_le_bytes());
+        out.extend_from_slice(&self.format.sample_rate.to_le_bytes());
+        out.extend_from_slice(&byte_rate.to_le_bytes());
+        out.extend_from_slice(&block_align.to_le_bytes());
+        out.extend_from_slice(&self.format.bits_per_sample.to_le_bytes());
+
+        out.extend_from_slice(b"data");
+        out.extend_from_slice(&data_len.to_le_bytes());
+        out.extend_from_slice(&self.data);
+        out
+    }
 
-        result
+    /// Mono 16-bit PCM at `target_rate` (8 or 16 kHz)
+    pub fn to_telephony(&self, target_rate: u32) -> Self {
+        let source = WavReader {
+            reader: &self.data[..],
+            format: self.format.clone(),
+            remaining: self.data.len() as u64,
+            data_len: self.data.len() as u64,
+            buffer: Vec::new(),
+        };
+        // Reading from memory cannot fail
+        Self::from_reader_converted(source, target_rate)
+            .unwrap_or_else(|_| Self::from_samples(WavFormat::telephony(target_rate), &[]))
+    }
+
+    /// Convert to G.711 compatible format (8kHz, mono, 16-bit)
+    pub fn to_g711_compatible(&self) -> Self {
+        self.to_telephony(NARROWBAND_RATE)
     }
 }
 
@@ -486,4 +963,221 @@ mod tests {
         assert_eq!(mono_samples[0], 150); // (100 + 200) / 2
         assert_eq!(mono_samples[1], 350); // (300 + 400) / 2
     }
+
+    /// 440 Hz left and 1 kHz right, 0.1 s at 44.1 kHz, 16-bit
+    const STEREO_44K1: &[u8] = include_bytes!("../../../tests/fixtures/audio/tones_44k1_stereo.wav");
+    /// The same tones mixed to mono, computed at 8 kHz from their formulas
+    const MONO_8K_REFERENCE: &[u8] = include_bytes!("../../../tests/fixtures/audio/tones_8k_mono.wav");
+
+    /// WAV bytes with a fmt chunk for `format_tag`; `sub_format` makes it
+    /// an extensible one
+    fn wav_bytes(
+        format_tag: u16,
+        sub_format: Option<u16>,
+        channels: u16,
+        sample_rate: u32,
+        bits: u16,
+        data: &[u8],
+    ) -> Vec<u8> {
+        let block_align = channels * bits / 8;
+        let mut fmt = Vec::new();
+        fmt.extend_from_slice(&format_tag.to_le_bytes());
+        fmt.extend_from_slice(&channels.to_le_bytes());
+        fmt.extend_from_slice(&sample_rate.to_le_bytes());
+        fmt.extend_from_slice(&(sample_rate * block_align as u32).to_le_bytes());
+        fmt.extend_from_slice(&block_align.to_le_bytes());
+        fmt.extend_from_slice(&bits.to_le_bytes());
+        if let Some(sub_format) = sub_format {
+            fmt.extend_from_slice(&22u16.to_le_bytes());
+            fmt.extend_from_slice(&bits.to_le_bytes());
+            fmt.extend_from_slice(&3u32.to_le_bytes());
+            fmt.extend_from_slice(&sub_format.to_le_bytes());
+            fmt.extend_from_slice(&SUBFORMAT_GUID_TAIL);
+        }
+
+        let mut out = Vec::new();
+        out.extend_from_slice(b"RIFF");
+        out.extend_from_slice(&((4 + 8 + 16 + 8 + fmt.len() + data.len()) as u32).to_le_bytes());
+        out.extend_from_slice(b"WAVE");
+        // Chunks readers must skip
+        out.extend_from_slice(b"LIST");
+        out.extend_from_slice(&4u32.to_le_bytes());
+        out.extend_from_slice(b"INFO");
+        out.extend_from_slice(b"fmt ");
+        out.extend_from_slice(&(fmt.len() as u32).to_le_bytes());
+        out.extend_from_slice(&fmt);
+        out.extend_from_slice(b"data");
+        out.extend_from_slice(&(data.len() as u32).to_le_bytes());
+        out.extend_from_slice(data);
+        out
+    }
+
+    fn tone(rate: u32, frequency: f64, samples: usize) -> Vec<f32> {
+        (0..samples)
+            .map(|i| (0.5 * (2.0 * std::f64::consts::PI * frequency * i as f64 / rate as f64).sin()) as f32)
+            .collect()
+    }
+
+    #[test]
+    fn test_golden_44k1_stereo_to_8k_mono() {
+        let converter =
+            TelephonyConverter::new(WavReader::new(STEREO_44K1).unwrap(), NARROWBAND_RATE).unwrap();
+        assert_eq!(converter.source_format().sample_rate, 44100);
+        assert_eq!(converter.source_format().channels, 2);
+        let converted = converter.read_to_end().unwrap();
+
+        let reference = WavFile::from_reader(&mut &MONO_8K_REFERENCE[..])
+            .unwrap()
+            .samples_i16();
+        assert_eq!(converted.len(), reference.len());
+
+        // The first and last 10 ms see the silence around the file
+        let edge = 80;
+        let diffs: Vec<f64> = converted[edge..converted.len() - edge]
+            .iter()
+            .zip(&reference[edge..reference.len() - edge])
+            .map(|(&a, &b)| (a as f64 - b as f64).abs())
+            .collect();
+        let max = diffs.iter().cloned().fold(0.0, f64::max);
+        let rms = (diffs.iter().map(|d| d * d).sum::<f64>() / diffs.len() as f64).sqrt();
+        assert!(max <= 50.0, "max difference {}", max);
+        assert!(rms <= 10.0, "rms difference {}", rms);
+    }
+
+    #[test]
+    fn test_streaming_matches_whole_file() {
+        let whole = TelephonyConverter::new(WavReader::new(STEREO_44K1).unwrap(), WIDEBAND_RATE)
+            .unwrap()
+            .read_to_end()
+            .unwrap();
+
+        // Feed the resampler blocks of odd sizes
+        let mut reader = WavReader::new(STEREO_44K1).unwrap();
+        let mut resampler = Resampler::new(44100, WIDEBAND_RATE);
+        let mut output = Vec::new();
+        for size in [1, 7, 100, 333, 4096].iter().cycle() {
+            let mut mono = Vec::new();
+            if reader.read_mono(*size, &mut mono).unwrap() == 0 {
+                break;
+            }
+            resampler.process(&mono, &mut output);
+        }
+        resampler.finish(&mut output);
+
+        let streamed: Vec<i16> = output.into_iter().map(to_i16).collect();
+        assert_eq!(streamed, whole);
+        assert_eq!(whole.len(), 1600);
+    }
+
+    #[test]
+    fn test_integer_and_upsampling_ratios() {
+        // 16 kHz -> 8 kHz keeps a 1 kHz tone and removes a 6 kHz one
+        for (frequency, expected_peak) in [(1000.0, 0.5), (6000.0, 0.0)] {
+            let mut resampler = Resampler::new(16000, 8000);
+            let mut output = Vec::new();
+            resampler.process(&tone(16000, frequency, 1600), &mut output);
+            resampler.finish(&mut output);
+            assert_eq!(output.len(), 800);
+            let peak = output[100..700].iter().fold(0.0f32, |m, s| m.max(s.abs()));
+            assert!((peak - expected_peak).abs() < 0.01, "{} Hz peak {}", frequency, peak);
+        }
+
+        // 8 kHz -> 16 kHz doubles the samples of the same tone
+        let mut resampler = Resampler::new(8000, 16000);
+        let mut output = Vec::new();
+        resampler.process(&tone(8000, 1000.0, 800), &mut output);
+        resampler.finish(&mut output);
+        assert_eq!(output.len(), 1600);
+        let expected = tone(16000, 1000.0, 1600);
+        for i in 200..1400 {
+            assert!((output[i] - expected[i]).abs() < 0.01, "sample {}", i);
+        }
+    }
+
+    #[test]
+    fn test_decodes_pcm_and_float_formats() {
+        let expected = [0.5f32, -0.25];
+
+        // 24-bit PCM
+        let mut data = Vec::new();
+        for s in [0x40_0000i32, -0x20_0000] {
+            data.extend_from_slice(&s.to_le_bytes()[..3]);
+        }
+        let pcm24 = wav_bytes(WAVE_FORMAT_PCM, None, 1, 48000, 24, &data);
+        // 32-bit float, extensible
+        let mut data = Vec::new();
+        for s in expected {
+            data.extend_from_slice(&s.to_le_bytes());
+        }
+        let float = wav_bytes(WAVE_FORMAT_EXTENSIBLE, Some(WAVE_FORMAT_IEEE_FLOAT), 1, 48000, 32, &data);
+        // 64-bit float
+        let mut data = Vec::new();
+        for s in expected {
+            data.extend_from_slice(&(s as f64).to_le_bytes());
+        }
+        let double = wav_bytes(WAVE_FORMAT_IEEE_FLOAT, None, 1, 48000, 64, &data);
+        // 8-bit PCM, unsigned
+        let pcm8 = wav_bytes(WAVE_FORMAT_PCM, None, 1, 8000, 8, &[192, 96]);
+
+        for bytes in [pcm24, float, double, pcm8] {
+            let mut reader = WavReader::new(&bytes[..]).unwrap();
+            let mut samples = Vec::new();
+            assert_eq!(reader.read_frames(16, &mut samples).unwrap(), 2);
+            assert_eq!(samples, expected, "{:?}", reader.format());
+            assert_eq!(reader.read_frames(16, &mut samples).unwrap(), 0);
+        }
+
+        let wav = WavFile::from_reader(&mut &wav_bytes(
+            WAVE_FORMAT_EXTENSIBLE,
+            Some(WAVE_FORMAT_IEEE_FLOAT),
+            1,
+            48000,
+            32,
+            &data_f32(&expected),
+        )[..])
+        .unwrap();
+        assert!(wav.format.is_float());
+        assert_eq!(wav.samples_i16(), vec![16384, -8192]);
+    }
+
+    fn data_f32(samples: &[f32]) -> Vec<u8> {
+        samples.iter().flat_map(|s| s.to_le_bytes()).collect()
+    }
+
+    #[test]
+    fn test_stereo_downmix_while_reading() {
+        let mut data = Vec::new();
+        for s in [1000i16, 3000, -2000, 0] {
+            data.extend_from_slice(&s.to_le_bytes());
+        }
+        let bytes = wav_bytes(WAVE_FORMAT_PCM, None, 2, 8000, 16, &data);
+        let converted = TelephonyConverter::new(WavReader::new(&bytes[..]).unwrap(), NARROWBAND_RATE)
+            .unwrap()
+            .read_to_end()
+            .unwrap();
+        assert_eq!(converted, vec![2000, -1000]);
+    }
+
+    #[test]
+    fn test_compressed_formats_are_named() {
+        let gsm = wav_bytes(0x0031, None, 1, 8000, 0, &[0; 65]);
+        assert_eq!(
+            WavReader::new(&gsm[..]).err(),
+            Some(WavError::UnsupportedEncoding {
+                format_tag: 0x0031,
+                name: "GSM 6.10".to_string(),
+            })
+        );
+        let adpcm = wav_bytes(0x0011, None, 1, 8000, 4, &[0; 256]);
+        let err = WavFile::from_reader(&mut &adpcm[..]).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "IMA ADPCM encoded WAV files are not supported; convert to PCM (16-bit) WAV"
+        );
+        let unknown = wav_bytes(WAVE_FORMAT_EXTENSIBLE, Some(0x0055), 2, 44100, 16, &[]);
+        assert!(matches!(
+            WavReader::new(&unknown[..]).err(),
+            Some(WavError::UnsupportedEncoding { format_tag: 0x0055, .. })
+        ));
+    }
 }
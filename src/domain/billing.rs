@@ -1,3 +1,4 @@
+use crate::domain::cdr::{CallDetailRecord, CallDirection};
 use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
@@ -102,6 +103,17 @@ impl Rate {
     }
 }
 
+/// Time of a call that a rate plan bills
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum BillableBase {
+    /// From the supervision point to the end of the call
+    #[default]
+    Connected,
+    /// As connected, less the time on hold
+    TalkTime,
+}
+
 /// Rate plan configuration
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct RatePlan {
@@ -112,6 +124,8 @@ pub struct RatePlan {
     pub billing_cycle: BillingCycle,
     pub monthly_fee: f64,
     pub rates: Vec<Rate>,
+    #[serde(default)]
+    pub billable_base: BillableBase,
     pub active: bool,
     pub created_at: DateTime<Utc>,
 }
@@ -126,6 +140,7 @@ impl RatePlan {
             billing_cycle,
             monthly_fee: 0.0,
             rates: Vec::new(),
+            billable_base: BillableBase::default(),
             active: true,
             created_at: Utc::now(),
         }
@@ -141,6 +156,12 @@ impl RatePlan {
         self
     }
 
+    /// Bill calls on `base`
+    pub fn with_billable_base(mut self, base: BillableBase) -> Self {
+        self.billable_base = base;
+        self
+    }
+
     pub fn add_rate(mut self, rate: Rate) -> Self {
         self.rates.push(rate);
         self
@@ -474,6 +495,87 @@ impl BillingAccount {
     }
 }
 
+/// Event a call is billed from; early media (183) never starts billing
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SupervisionPoint {
+    /// The 200 OK answering the call
+    #[default]
+    Answer,
+    /// The caller's ACK of the 200 OK
+    AckComplete,
+}
+
+/// Answer supervision of a trunk's calls
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct AnswerSupervision {
+    pub bill_from: SupervisionPoint,
+    /// Calls hung up within this many seconds of the answer get a suspect
+    /// answer (e.g. a carrier announcement answering with 200 OK)
+    pub validation_window_secs: Option<u64>,
+}
+
+/// Answer supervision, per trunk
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct AnswerSupervisionConfig {
+    /// Calls not through a trunk listed in `trunks`
+    pub default: AnswerSupervision,
+    /// Per-trunk settings, keyed by trunk name
+    pub trunks: HashMap<String, AnswerSupervision>,
+    /// Answered seconds up to which the suspect calls report lists a call
+    /// when no threshold is asked for
+    pub report_threshold_secs: u64,
+}
+
+impl Default for AnswerSupervisionConfig {
+    fn default() -> Self {
+        Self {
+            default: AnswerSupervision::default(),
+            trunks: HashMap::new(),
+            report_threshold_secs: 6,
+        }
+    }
+}
+
+impl AnswerSupervisionConfig {
+    /// Supervision of calls through `trunk`
+    pub fn for_trunk(&self, trunk: Option<&str>) -> &AnswerSupervision {
+        trunk
+            .and_then(|trunk| self.trunks.get(trunk))
+            .unwrap_or(&self.default)
+    }
+
+    /// Whether an ended call was hung up within its trunk's validation
+    /// window after the answer
+    pub fn is_suspect(&self, cdr: &CallDetailRecord) -> bool {
+        let Some(window) = self.for_trunk(cdr.trunk.as_deref()).validation_window_secs else {
+            return false;
+        };
+        cdr.answer_time.is_some()
+            && cdr.end_time.is_some()
+            && cdr.call_duration.unwrap_or(0).max(0) as u64 <= window
+    }
+
+    pub fn validate(&self) -> Result<(), String> {
+        if self.report_threshold_secs == 0 {
+            return Err("report_threshold_secs must be at least 1".to_string());
+        }
+        let windows = std::iter::once(("default", &self.default))
+            .chain(self.trunks.iter().map(|(name, s)| (name.as_str(), s)));
+        for (name, supervision) in windows {
+            if supervision.validation_window_secs == Some(0) {
+                return Err(format!(
+                    "{}: validation_window_secs must be at least 1 (or unset)",
+                    name
+                ));
+            }
+        }
+        Ok(())
+    }
+}
+
 /// Billing manager for handling all billing operations
 pub struct BillingManager {
     accounts: Arc<Mutex<HashMap<Uuid, BillingAccount>>>,
@@ -482,6 +584,7 @@ pub struct BillingManager {
     invoices: Arc<Mutex<HashMap<Uuid, Invoice>>>,
     payments: Arc<Mutex<Vec<Payment>>>,
     next_invoice_number: Arc<Mutex<u64>>,
+    supervision: AnswerSupervisionConfig,
 }
 
 impl BillingManager {
@@ -493,9 +596,16 @@ impl BillingManager {
             invoices: Arc::new(Mutex::new(HashMap::new())),
             payments: Arc::new(Mutex::new(Vec::new())),
             next_invoice_number: Arc::new(Mutex::new(1)),
+            supervision: AnswerSupervisionConfig::default(),
         }
     }
 
+    /// Bill each trunk's calls from its configured supervision point
+    pub fn with_supervision(mut self, supervision: AnswerSupervisionConfig) -> Self {
+        self.supervision = supervision;
+        self
+    }
+
     /// Create a new billing account
     pub fn create_account(&self, account: BillingAccount) -> Uuid {
         let account_id = account.id;
@@ -573,6 +683,40 @@ impl BillingManager {
         Ok(record_id)
     }
 
+    /// Record the minutes of a finished call, by its direction
+    ///
+    /// Minutes count from the supervision point of the call's trunk.
+    /// Unanswered calls, calls that never reached that point and test calls
+    /// are not billed (`None`).
+    pub fn record_call(
+        &self,
+        account_id: Uuid,
+        cdr: &CallDetailRecord,
+    ) -> Result<Option<Uuid>, String> {
+        if !cdr.is_billable() {
+            return Ok(None);
+        }
+        let point = self.supervision.for_trunk(cdr.trunk.as_deref()).bill_from;
+        let Some(mut seconds) = cdr.billable_seconds(point) else {
+            return Ok(None);
+        };
+        let base = self
+            .get_account(&account_id)
+            .and_then(|account| self.get_rate_plan(&account.rate_plan_id))
+            .map(|plan| plan.billable_base)
+            .unwrap_or_default();
+        if base == BillableBase::TalkTime {
+            seconds = (seconds - cdr.hold_duration).max(0);
+        }
+        let usage_type = match cdr.direction {
+            CallDirection::Inbound => UsageType::InboundMinutes,
+            CallDirection::Outbound => UsageType::OutboundMinutes,
+            CallDirection::Internal => UsageType::InternalMinutes,
+        };
+        let minutes = seconds as f64 / 60.0;
+        self.record_usage(account_id, usage_type, minutes).map(Some)
+    }
+
     /// Generate invoice for an account
     pub fn generate_invoice(
         &self,
@@ -873,6 +1017,121 @@ mod tests {
         assert_eq!(balance, Some(10.0));
     }
 
+    #[test]
+    fn test_test_calls_are_not_billed() {
+        let manager = BillingManager::new();
+        let plan = RatePlan::new("Test Plan".to_string(), Currency::USD, BillingCycle::Monthly)
+            .add_rate(Rate::new(UsageType::OutboundMinutes, 0.10));
+        let plan_id = manager.create_rate_plan(plan);
+        let account_id = manager.create_account(BillingAccount::new(
+            Uuid::new_v4(),
+            plan_id,
+            Currency::USD,
+            "test@example.com".to_string(),
+        ));
+
+        let mut cdr = CallDetailRecord::new(
+            "call-1".to_string(),
+            "alice".to_string(),
+            "sip:alice@example.com".to_string(),
+            "192.168.1.100".to_string(),
+            "bob".to_string(),
+            "sip:bob@example.com".to_string(),
+            CallDirection::Outbound,
+        );
+        cdr.mark_answered();
+        cdr.call_duration = Some(600);
+        assert!(manager.record_call(account_id, &cdr).unwrap().is_some());
+        assert_eq!(manager.get_account_balance(&account_id), Some(1.0));
+
+        cdr.mark_test_call();
+        assert_eq!(manager.record_call(account_id, &cdr).unwrap(), None);
+        assert_eq!(manager.get_account_balance(&account_id), Some(1.0));
+    }
+
+    #[test]
+    fn test_trunk_bills_from_its_supervision_point() {
+        let mut supervision = AnswerSupervisionConfig::default();
+        supervision.trunks.insert(
+            "carrier-a".to_string(),
+            AnswerSupervision {
+                bill_from: SupervisionPoint::AckComplete,
+                validation_window_secs: None,
+            },
+        );
+        let manager = BillingManager::new().with_supervision(supervision);
+        let plan = RatePlan::new("Test Plan".to_string(), Currency::USD, BillingCycle::Monthly)
+            .add_rate(Rate::new(UsageType::OutboundMinutes, 0.10));
+        let plan_id = manager.create_rate_plan(plan);
+        let account_id = manager.create_account(BillingAccount::new(
+            Uuid::new_v4(),
+            plan_id,
+            Currency::USD,
+            "test@example.com".to_string(),
+        ));
+
+        let mut cdr = CallDetailRecord::new(
+            "call-1".to_string(),
+            "alice".to_string(),
+            "sip:alice@example.com".to_string(),
+            "192.168.1.100".to_string(),
+            "+14155551234".to_string(),
+            "sip:+14155551234@carrier-a.example.net".to_string(),
+            CallDirection::Outbound,
+        );
+        cdr.set_trunk("carrier-a".to_string());
+        let answered = Utc::now();
+        cdr.mark_early_media(answered - chrono::Duration::seconds(20));
+        cdr.answer_time = Some(answered);
+        cdr.call_duration = Some(62);
+
+        // Answered but never acknowledged: nothing billed
+        assert_eq!(manager.record_call(account_id, &cdr).unwrap(), None);
+
+        cdr.mark_ack_complete(answered + chrono::Duration::seconds(2));
+        cdr.end_time = Some(answered + chrono::Duration::seconds(62));
+        assert!(manager.record_call(account_id, &cdr).unwrap().is_some());
+        assert!((manager.get_account_balance(&account_id).unwrap() - 0.10).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_talk_time_plan_does_not_bill_hold() {
+        let manager = BillingManager::new();
+        let mut accounts = Vec::new();
+        for base in [BillableBase::Connected, BillableBase::TalkTime] {
+            let plan = RatePlan::new("Test Plan".to_string(), Currency::USD, BillingCycle::Monthly)
+                .with_billable_base(base)
+                .add_rate(Rate::new(UsageType::OutboundMinutes, 0.10));
+            let plan_id = manager.create_rate_plan(plan);
+            accounts.push(manager.create_account(BillingAccount::new(
+                Uuid::new_v4(),
+                plan_id,
+                Currency::USD,
+                "test@example.com".to_string(),
+            )));
+        }
+
+        let mut cdr = CallDetailRecord::new(
+            "call-1".to_string(),
+            "alice".to_string(),
+            "sip:alice@example.com".to_string(),
+            "192.168.1.100".to_string(),
+            "+14155551234".to_string(),
+            "sip:+14155551234@carrier-a.example.net".to_string(),
+            CallDirection::Outbound,
+        );
+        cdr.mark_answered();
+        cdr.call_duration = Some(600);
+        cdr.hold_duration = 240;
+        for account_id in &accounts {
+            manager.record_call(*account_id, &cdr).unwrap();
+        }
+
+        // 10 minutes connected, 6 of them talking
+        assert!((manager.get_account_balance(&accounts[0]).unwrap() - 1.0).abs() < 1e-9);
+        assert!((manager.get_account_balance(&accounts[1]).unwrap() - 0.6).abs() < 1e-9);
+    }
+
     #[test]
     fn test_invoice_generation() {
         let manager = BillingManager::new();
@@ -2,8 +2,8 @@
 
 use crate::domain::call::entity::Participant;
 use crate::domain::call::event::{
-    CallAnswered, CallEnded, CallEvent, CallEventBase, CallHeld, CallInitiated, CallResumed,
-    CallRinging,
+    CallAcknowledged, CallAnswered, CallEarlyMedia, CallEnded, CallEvent, CallEventBase, CallHeld,
+    CallInitiated, CallResumed, CallRinging,
 };
 use crate::domain::call::value_object::{CallDirection, CallState, EndReason};
 use crate::domain::shared::error::DomainError;
@@ -35,8 +35,20 @@ pub struct Call {
     started_at: DateTime<Utc>,
     /// When the call was answered (if applicable)
     answered_at: Option<DateTime<Utc>>,
+    /// When the callee first sent early media
+    #[serde(default)]
+    early_media_at: Option<DateTime<Utc>>,
+    /// When the caller acknowledged the answer
+    #[serde(default)]
+    acknowledged_at: Option<DateTime<Utc>>,
     /// When the call ended (if applicable)
     ended_at: Option<DateTime<Utc>>,
+    /// Events recorded over the call's life, published or not
+    #[serde(default)]
+    recorded_events: u64,
+    /// Version of the stored call this was loaded or saved as; 0 until saved
+    #[serde(skip)]
+    version: u64,
     /// Pending domain events
     #[serde(skip)]
     events: Vec<CallEvent>,
@@ -60,7 +72,11 @@ impl Call {
             callee: callee.clone(),
             started_at,
             answered_at: None,
+            early_media_at: None,
+            acknowledged_at: None,
             ended_at: None,
+            recorded_events: 0,
+            version: 0,
             events: Vec::new(),
         };
 
@@ -111,6 +127,54 @@ impl Call {
         Ok(())
     }
 
+    /// The callee sent early media; only the first is recorded
+    pub fn early_media(&mut self) -> Result<()> {
+        if !matches!(self.state, CallState::Initiating | CallState::Ringing) {
+            return Err(DomainError::InvalidStateTransition(
+                "Early media only precedes the answer".to_string(),
+            ));
+        }
+        if self.early_media_at.is_some() {
+            return Ok(());
+        }
+        let started_at = Utc::now();
+        self.early_media_at = Some(started_at);
+
+        self.record_event(CallEvent::EarlyMedia(CallEarlyMedia {
+            base: CallEventBase {
+                metadata: EventMetadata::new("call.early_media".to_string()),
+                call_id: self.id,
+            },
+            started_at,
+        }));
+
+        Ok(())
+    }
+
+    /// The caller acknowledged the answer; retransmitted ACKs are ignored
+    pub fn acknowledge(&mut self) -> Result<()> {
+        if !matches!(self.state, CallState::Answered | CallState::OnHold) {
+            return Err(DomainError::InvalidStateTransition(
+                "Only an answered call is acknowledged".to_string(),
+            ));
+        }
+        if self.acknowledged_at.is_some() {
+            return Ok(());
+        }
+        let acknowledged_at = Utc::now();
+        self.acknowledged_at = Some(acknowledged_at);
+
+        self.record_event(CallEvent::Acknowledged(CallAcknowledged {
+            base: CallEventBase {
+                metadata: EventMetadata::new("call.acknowledged".to_string()),
+                call_id: self.id,
+            },
+            acknowledged_at,
+        }));
+
+        Ok(())
+    }
+
     /// Put the call on hold
     pub fn hold(&mut self) -> Result<()> {
         self.transition_to(CallState::OnHold)?;
@@ -183,6 +247,7 @@ impl Call {
 
     /// Record a domain event
     fn record_event(&mut self, event: CallEvent) {
+        self.recorded_events += 1;
         self.events.push(event);
     }
 
@@ -224,6 +289,14 @@ impl Call {
         self.answered_at.as_ref()
     }
 
+    pub fn early_media_at(&self) -> Option<&DateTime<Utc>> {
+        self.early_media_at.as_ref()
+    }
+
+    pub fn acknowledged_at(&self) -> Option<&DateTime<Utc>> {
+        self.acknowledged_at.as_ref()
+    }
+
     pub fn ended_at(&self) -> Option<&DateTime<Utc>> {
         self.ended_at.as_ref()
     }
@@ -237,6 +310,20 @@ impl Call {
     pub fn is_active(&self) -> bool {
         self.state.is_active()
     }
+
+    /// Number of events recorded so far; the pending events are the last ones
+    pub fn recorded_events(&self) -> u64 {
+        self.recorded_events
+    }
+
+    pub fn version(&self) -> u64 {
+        self.version
+    }
+
+    /// Set by repositories when the call is loaded or saved
+    pub fn set_version(&mut self, version: u64) {
+        self.version = version;
+    }
 }
 
 #[cfg(test)]
@@ -295,6 +382,26 @@ mod tests {
         // Verify all events were recorded
         let events = call.take_events();
         assert_eq!(events.len(), 6); // Initiated, Ringing, Answered, Held, Resumed, Ended
+        assert_eq!(call.recorded_events(), 6);
+    }
+
+    #[test]
+    fn test_early_media_and_ack_are_recorded_once() {
+        let mut call = create_test_call();
+        call.ring(SessionId::new()).unwrap();
+        call.early_media().unwrap();
+        call.early_media().unwrap();
+        call.answer().unwrap();
+        assert!(call.early_media().is_err());
+        call.acknowledge().unwrap();
+        call.acknowledge().unwrap();
+        assert!(call.early_media_at().unwrap() <= call.acknowledged_at().unwrap());
+
+        let events = call.take_events();
+        // Initiated, Ringing, EarlyMedia, Answered, Acknowledged
+        assert_eq!(events.len(), 5);
+        assert!(matches!(events[2], CallEvent::EarlyMedia(_)));
+        assert!(matches!(events[4], CallEvent::Acknowledged(_)));
     }
 
     #[test]
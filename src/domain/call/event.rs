@@ -67,6 +67,40 @@ impl DomainEvent for CallAnswered {
     }
 }
 
+/// Early media from the callee (183 with SDP); only the first is recorded
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CallEarlyMedia {
+    pub base: CallEventBase,
+    pub started_at: DateTime<Utc>,
+}
+
+impl DomainEvent for CallEarlyMedia {
+    fn event_type(&self) -> &'static str {
+        "call.early_media"
+    }
+
+    fn occurred_at(&self) -> DateTime<Utc> {
+        self.base.metadata.occurred_at
+    }
+}
+
+/// ACK of the answer received: the call is established
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CallAcknowledged {
+    pub base: CallEventBase,
+    pub acknowledged_at: DateTime<Utc>,
+}
+
+impl DomainEvent for CallAcknowledged {
+    fn event_type(&self) -> &'static str {
+        "call.acknowledged"
+    }
+
+    fn occurred_at(&self) -> DateTime<Utc> {
+        self.base.metadata.occurred_at
+    }
+}
+
 /// Call held event
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct CallHeld {
@@ -120,10 +154,13 @@ impl DomainEvent for CallEnded {
 
 /// Union of all call events
 #[derive(Debug, Clone, Serialize, Deserialize)]
+#[allow(clippy::large_enum_variant)]
 pub enum CallEvent {
     Initiated(CallInitiated),
     Ringing(CallRinging),
+    EarlyMedia(CallEarlyMedia),
     Answered(CallAnswered),
+    Acknowledged(CallAcknowledged),
     Held(CallHeld),
     Resumed(CallResumed),
     Ended(CallEnded),
@@ -134,7 +171,9 @@ impl CallEvent {
         match self {
             CallEvent::Initiated(e) => &e.base.call_id,
             CallEvent::Ringing(e) => &e.base.call_id,
+            CallEvent::EarlyMedia(e) => &e.base.call_id,
             CallEvent::Answered(e) => &e.base.call_id,
+            CallEvent::Acknowledged(e) => &e.base.call_id,
             CallEvent::Held(e) => &e.base.call_id,
             CallEvent::Resumed(e) => &e.base.call_id,
             CallEvent::Ended(e) => &e.base.call_id,
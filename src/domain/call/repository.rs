@@ -2,20 +2,25 @@
 
 use crate::domain::call::aggregate::Call;
 use crate::domain::shared::result::Result;
-use crate::domain::shared::value_objects::CallId;
+use crate::domain::shared::value_objects::{CallId, SipUri};
 use async_trait::async_trait;
 
 /// Repository interface for Call aggregate
 ///
 /// This is defined in the domain layer as a trait (port),
 /// and implemented in the infrastructure layer (adapter).
+///
+/// Saves are optimistic: a call is saved only if the stored call is still
+/// at the version it was loaded at (a new call, version 0, only if none is
+/// stored), otherwise `DomainError::Conflict` is returned and the caller
+/// reloads.
 #[async_trait]
 pub trait CallRepository: Send + Sync {
     /// Find a call by its ID
     async fn find_by_id(&self, id: &CallId) -> Result<Option<Call>>;
 
-    /// Save a call (insert or update)
-    async fn save(&self, call: &Call) -> Result<()>;
+    /// Save a call (insert or update), advancing its version
+    async fn save(&self, call: &mut Call) -> Result<()>;
 
     /// Delete a call
     async fn delete(&self, id: &CallId) -> Result<()>;
@@ -25,4 +30,8 @@ pub trait CallRepository: Send + Sync {
 
     /// Find calls by endpoint ID
     async fn find_by_endpoint(&self, endpoint_id: &crate::domain::shared::value_objects::EndpointId) -> Result<Vec<Call>>;
+
+    /// Find active calls with a caller or callee at the address of record
+    /// of `uri`
+    async fn find_active_by_participant(&self, uri: &SipUri) -> Result<Vec<Call>>;
 }
@@ -89,6 +89,10 @@ pub enum EndReason {
     Failed(String),
     /// Call was canceled
     Canceled,
+    /// Ended by the PBX for maintenance of a tenant or trunk
+    Maintenance,
+    /// Diverted to the callee's voicemail instead of being answered
+    Voicemail,
 }
 
 #[cfg(test)]
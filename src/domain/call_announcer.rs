@@ -35,10 +35,14 @@ pub struct AnnouncementRequest {
     pub audio_files: Vec<String>,
     /// Language for announcements
     pub language: Language,
+    /// Prompt set tried before the stock prompts (`<set>/<file id>`)
+    pub prompt_set: Option<String>,
     /// When to play (None = immediately)
     pub play_at: Option<Instant>,
     /// Repeat interval (for periodic announcements)
     pub repeat_interval: Option<Duration>,
+    /// URI of the party to play to (None = both parties)
+    pub party: Option<String>,
 }
 
 impl AnnouncementRequest {
@@ -50,8 +54,10 @@ impl AnnouncementRequest {
             announcement_type,
             audio_files: Vec::new(),
             language: Language::En,
+            prompt_set: None,
             play_at: None,
             repeat_interval: None,
+            party: None,
         }
     }
 
@@ -67,6 +73,12 @@ impl AnnouncementRequest {
         self
     }
 
+    /// Prefer the prompts of a set, e.g. a tenant's own recordings
+    pub fn with_prompt_set(mut self, prompt_set: &str) -> Self {
+        self.prompt_set = Some(prompt_set.to_string());
+        self
+    }
+
     /// Play immediately
     pub fn immediate(mut self) -> Self {
         self.play_at = None;
@@ -79,6 +91,12 @@ impl AnnouncementRequest {
         self
     }
 
+    /// Play to one party of the call only
+    pub fn to_party(mut self, uri: &str) -> Self {
+        self.party = Some(uri.to_string());
+        self
+    }
+
     /// Repeat periodically
     pub fn repeat_every(mut self, interval: Duration) -> Self {
         self.repeat_interval = Some(interval);
@@ -152,8 +170,14 @@ impl CallAnnouncer {
         let mut builder = SequenceBuilder::new();
 
         for file_id in &request.audio_files {
-            let audio = self.audio_manager
-                .get_with_fallback(file_id, request.language)
+            let audio = request
+                .prompt_set
+                .as_ref()
+                .and_then(|set| {
+                    self.audio_manager
+                        .get_with_fallback(&format!("{}/{}", set, file_id), request.language)
+                })
+                .or_else(|| self.audio_manager.get_with_fallback(file_id, request.language))
                 .ok_or_else(|| format!("Audio file not found: {}", file_id))?;
             builder = builder.add(audio);
         }
@@ -4,7 +4,8 @@
 //! busy forwarding, no-answer forwarding, and conditional forwarding based on
 //! various criteria like time of day, caller ID, etc.
 
-use chrono::{DateTime, NaiveTime, Utc, Weekday};
+use crate::domain::shared::NumberingPlan;
+use chrono::{DateTime, Datelike, NaiveTime, Utc, Weekday};
 use serde::{Deserialize, Serialize};
 use std::collections::{HashMap, HashSet};
 use std::sync::{Arc, Mutex};
@@ -163,7 +164,7 @@ impl CallerFilter {
         self
     }
 
-    /// Check if the caller matches the filter
+    /// Check if the caller matches the filter, comparing raw strings
     pub fn matches(&self, caller: &str) -> bool {
         if self.exact_match {
             self.allowed_callers.iter().any(|c| c == caller)
@@ -173,6 +174,34 @@ impl CallerFilter {
                 .any(|c| caller.starts_with(c) || c.starts_with(caller))
         }
     }
+
+    /// Check if the caller matches the filter, whatever form the caller
+    /// ID and the entries are written in
+    ///
+    /// Prefixes written with `+` are compared against the caller's E.164
+    /// form; other entries match as in [`Self::matches`].
+    pub fn matches_number(&self, caller: &str, plan: &NumberingPlan, tenant: Option<&str>) -> bool {
+        if self.matches(caller) {
+            return true;
+        }
+        if self.exact_match {
+            return self
+                .allowed_callers
+                .iter()
+                .any(|c| plan.same_number(c, caller, tenant));
+        }
+
+        let Some(e164) = plan.normalize(caller, tenant) else {
+            return false;
+        };
+        self.allowed_callers.iter().any(|c| {
+            let prefix: String = c
+                .chars()
+                .filter(|c| c.is_ascii_digit() || *c == '+')
+                .collect();
+            prefix.len() > 1 && prefix.starts_with('+') && e164.starts_with(&prefix)
+        })
+    }
 }
 
 impl Default for CallerFilter {
@@ -268,6 +297,18 @@ impl ForwardingRule {
 
     /// Check if this rule should be applied given the current context
     pub fn should_apply(&self, caller: &str, current_time: DateTime<Utc>) -> bool {
+        self.should_apply_with(caller, current_time, None, None)
+    }
+
+    /// Like [`Self::should_apply`], matching the caller filter's numbers
+    /// with `plan` for the tenant of the rule's user
+    pub fn should_apply_with(
+        &self,
+        caller: &str,
+        current_time: DateTime<Utc>,
+        plan: Option<&NumberingPlan>,
+        tenant: Option<&str>,
+    ) -> bool {
         if !self.enabled {
             return false;
         }
@@ -283,7 +324,11 @@ impl ForwardingRule {
 
         // Check caller filter for caller-based forwarding
         if let Some(ref caller_filter) = self.caller_filter {
-            if !caller_filter.matches(caller) {
+            let matches = match plan {
+                Some(plan) => caller_filter.matches_number(caller, plan, tenant),
+                None => caller_filter.matches(caller),
+            };
+            if !matches {
                 return false;
             }
         }
@@ -366,6 +411,8 @@ pub struct CallForwardingManager {
     forwarded_calls: Arc<Mutex<u64>>,
     /// Calls by type counter
     calls_by_type: Arc<Mutex<HashMap<String, u64>>>,
+    /// Matches caller filters by number rather than by string
+    numbering: Option<Arc<NumberingPlan>>,
 }
 
 impl CallForwardingManager {
@@ -374,9 +421,23 @@ impl CallForwardingManager {
             rules: Arc::new(Mutex::new(HashMap::new())),
             forwarded_calls: Arc::new(Mutex::new(0)),
             calls_by_type: Arc::new(Mutex::new(HashMap::new())),
+            numbering: None,
         }
     }
 
+    /// Compare caller IDs with caller filters as phone numbers
+    pub fn with_numbering_plan(mut self, numbering: Arc<NumberingPlan>) -> Self {
+        self.numbering = Some(numbering);
+        self
+    }
+
+    /// Rule applies to a call from `caller` to `user_id` (`user@realm`
+    /// picks the realm's home country)
+    fn rule_applies(&self, rule: &ForwardingRule, user_id: &str, caller: &str, now: DateTime<Utc>) -> bool {
+        let tenant = user_id.split_once('@').map(|(_, realm)| realm);
+        rule.should_apply_with(caller, now, self.numbering.as_deref(), tenant)
+    }
+
     /// Add a forwarding rule
     pub fn add_rule(&self, rule: ForwardingRule) -> Result<Uuid, String> {
         let mut rules = self.rules.lock().unwrap();
@@ -503,7 +564,7 @@ impl CallForwardingManager {
         user_rules
             .iter()
             .filter(|r| r.enabled && r.forwarding_type == forwarding_type)
-            .filter(|r| r.should_apply(caller, current_time))
+            .filter(|r| self.rule_applies(r, user_id, caller, current_time))
             .min_by_key(|r| r.priority)
             .map(|r| r.destination.clone())
     }
@@ -523,7 +584,7 @@ impl CallForwardingManager {
         user_rules
             .iter()
             .filter(|r| r.enabled)
-            .filter(|r| r.should_apply(caller, current_time))
+            .filter(|r| self.rule_applies(r, user_id, caller, current_time))
             .min_by_key(|r| r.priority)
             .map(|r| (r.forwarding_type, r.destination.clone()))
     }
@@ -737,6 +798,29 @@ mod tests {
         assert!(!filter.matches("200"));
     }
 
+    #[test]
+    fn test_caller_filter_number_match() {
+        let plan = NumberingPlan::default();
+        let filter = CallerFilter::new()
+            .add_caller("(415) 555-1234".to_string())
+            .add_caller("1001".to_string());
+
+        assert!(filter.matches_number("+14155551234", &plan, None));
+        assert!(filter.matches_number("sip:14155551234@trunk.example.net", &plan, None));
+        assert!(filter.matches_number("1001", &plan, None));
+        assert!(!filter.matches_number("+14155551235", &plan, None));
+        // Raw comparison does not see the same number
+        assert!(!filter.matches("+14155551234"));
+
+        let filter = CallerFilter::new()
+            .add_caller("+1 415".to_string())
+            .add_caller("100".to_string())
+            .with_prefix_matching();
+        assert!(filter.matches_number("4155550000", &plan, None));
+        assert!(filter.matches_number("1001", &plan, None));
+        assert!(!filter.matches_number("2125550000", &plan, None));
+    }
+
     #[test]
     fn test_forwarding_rule_creation() {
         let dest = ForwardingDestination::new("sip:voicemail@example.com".to_string());
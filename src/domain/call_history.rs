@@ -0,0 +1,422 @@
+//! Per-user call history
+//!
+//! A compact recent-calls view derived from CDRs, intended for phone displays
+//! and recent-calls sync. Forwarded/transferred call chains (CDR legs sharing a
+//! correlation id) are folded into a single logical entry, and each entry is
+//! classified from the user's point of view (answered, missed, rejected,
+//! voicemail).
+
+use crate::domain::cdr::{CallDetailRecord, CallStatus, END_REASON_VOICEMAIL};
+use crate::domain::shared::NumberingPlan;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashMap;
+use std::hash::{Hash, Hasher};
+use std::sync::{Arc, Mutex};
+use uuid::Uuid;
+
+/// Direction of a call relative to the user
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum HistoryDirection {
+    Incoming,
+    Outgoing,
+}
+
+/// Outcome of a call from the user's point of view
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum CallDisposition {
+    /// The user talked to the remote party
+    Answered,
+    /// Incoming call that rang but was never answered by the user
+    Missed,
+    /// Call was declined
+    Rejected,
+    /// Incoming call ended up in the user's voicemail
+    Voicemail,
+    /// Outgoing call the remote party never answered
+    Unanswered,
+}
+
+/// One logical call in a user's history
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct CallHistoryEntry {
+    /// ID of the first CDR leg of the call
+    pub id: Uuid,
+    pub direction: HistoryDirection,
+    /// Remote party number (username / user part of the URI)
+    pub remote_number: String,
+    /// Remote party display name, resolved from the user directory when internal
+    pub remote_name: Option<String>,
+    pub start_time: DateTime<Utc>,
+    /// Talk time in seconds
+    pub duration: i32,
+    pub disposition: CallDisposition,
+    /// SIP Call-IDs of every leg folded into this entry
+    pub call_ids: Vec<String>,
+}
+
+impl CallHistoryEntry {
+    pub fn is_missed(&self) -> bool {
+        self.disposition == CallDisposition::Missed
+    }
+}
+
+/// Classify a single CDR leg from the user's point of view
+///
+/// Returns None if the user is neither caller nor callee of the leg.
+pub fn classify_leg(
+    cdr: &CallDetailRecord,
+    username: &str,
+) -> Option<(HistoryDirection, CallDisposition)> {
+    let direction = if cdr.callee_username == username {
+        HistoryDirection::Incoming
+    } else if cdr.caller_username == username {
+        HistoryDirection::Outgoing
+    } else {
+        return None;
+    };
+
+    let voicemail = cdr.end_reason.as_deref() == Some(END_REASON_VOICEMAIL);
+
+    let disposition = match direction {
+        HistoryDirection::Incoming => {
+            if voicemail {
+                CallDisposition::Voicemail
+            } else if cdr.answer_time.is_some() {
+                CallDisposition::Answered
+            } else if cdr.status == CallStatus::Rejected {
+                CallDisposition::Rejected
+            } else {
+                // Rang (or was busy) but the user never picked up
+                CallDisposition::Missed
+            }
+        }
+        HistoryDirection::Outgoing => {
+            if cdr.answer_time.is_some() && !voicemail {
+                CallDisposition::Answered
+            } else if voicemail {
+                CallDisposition::Voicemail
+            } else if matches!(cdr.status, CallStatus::Rejected | CallStatus::Busy) {
+                CallDisposition::Rejected
+            } else {
+                CallDisposition::Unanswered
+            }
+        }
+    };
+
+    Some((direction, disposition))
+}
+
+/// Build a user's call history from CDRs
+///
+/// Legs sharing a correlation id are grouped into one entry. The entry's
+/// direction and remote party come from the earliest leg the user took part
+/// in; it counts as answered if the user answered (or got through on) any leg,
+/// so a call that was missed and then picked up after forwarding back is not
+/// reported as missed. Entries are returned newest first.
+pub fn build_call_history(
+    username: &str,
+    cdrs: &[CallDetailRecord],
+    limit: usize,
+) -> Vec<CallHistoryEntry> {
+    // Group legs into chains, preserving the first-seen order of each chain
+    let mut chains: Vec<Vec<&CallDetailRecord>> = Vec::new();
+    let mut chain_index: HashMap<&str, usize> = HashMap::new();
+
+    for cdr in cdrs {
+        match cdr.correlation_id.as_deref() {
+            Some(correlation_id) => match chain_index.get(correlation_id) {
+                Some(&index) => chains[index].push(cdr),
+                None => {
+                    chain_index.insert(correlation_id, chains.len());
+                    chains.push(vec![cdr]);
+                }
+            },
+            None => chains.push(vec![cdr]),
+        }
+    }
+
+    let mut entries: Vec<CallHistoryEntry> = chains
+        .into_iter()
+        .filter_map(|mut legs| {
+            legs.sort_by_key(|cdr| cdr.start_time);
+
+            let classified: Vec<_> = legs
+                .iter()
+                .filter_map(|cdr| classify_leg(cdr, username).map(|c| (*cdr, c)))
+                .collect();
+            let (first, (direction, first_disposition)) = *classified.first()?;
+
+            let disposition = if classified
+                .iter()
+                .any(|(_, (_, d))| *d == CallDisposition::Answered)
+            {
+                CallDisposition::Answered
+            } else if classified
+                .iter()
+                .any(|(_, (_, d))| *d == CallDisposition::Voicemail)
+            {
+                CallDisposition::Voicemail
+            } else {
+                first_disposition
+            };
+
+            let remote_number = match direction {
+                HistoryDirection::Incoming => first.caller_username.clone(),
+                HistoryDirection::Outgoing => first.callee_username.clone(),
+            };
+
+            let duration = classified
+                .iter()
+                .filter_map(|(cdr, _)| cdr.call_duration)
+                .sum();
+
+            Some(CallHistoryEntry {
+                id: first.id,
+                direction,
+                remote_number,
+                remote_name: None,
+                start_time: first.start_time,
+                duration,
+                disposition,
+                call_ids: legs.iter().map(|cdr| cdr.call_id.clone()).collect(),
+            })
+        })
+        .collect();
+
+    entries.sort_by_key(|e| std::cmp::Reverse(e.start_time));
+    entries.truncate(limit);
+    entries
+}
+
+/// Compute a strong ETag over a history listing
+///
+/// Phones poll the history frequently; an unchanged listing yields the same
+/// tag so the API can answer with 304 Not Modified.
+pub fn history_etag(entries: &[CallHistoryEntry], missed_since_last_read: usize) -> String {
+    let mut hasher = DefaultHasher::new();
+    missed_since_last_read.hash(&mut hasher);
+    for entry in entries {
+        entry.id.hash(&mut hasher);
+        entry.disposition.hash(&mut hasher);
+        entry.duration.hash(&mut hasher);
+        entry.remote_name.hash(&mut hasher);
+        entry.call_ids.hash(&mut hasher);
+    }
+    format!("\"{:016x}\"", hasher.finish())
+}
+
+/// Name of the contact whose number is `number`
+///
+/// `contacts` pairs names with numbers as the user saved them; the
+/// remote number of a call matches however either side is presented
+/// (`+14155551234` finds the contact saved as `(415) 555-1234`).
+pub fn contact_name<'a>(
+    number: &str,
+    contacts: &'a [(String, String)],
+    plan: &NumberingPlan,
+    tenant: Option<&str>,
+) -> Option<&'a str> {
+    contacts
+        .iter()
+        .find(|(_, contact)| plan.same_number(contact, number, tenant))
+        .map(|(name, _)| name.as_str())
+}
+
+/// Tracks when each user last read their missed calls
+pub struct MissedCallTracker {
+    last_read: Arc<Mutex<HashMap<String, DateTime<Utc>>>>,
+}
+
+impl MissedCallTracker {
+    pub fn new() -> Self {
+        Self {
+            last_read: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+
+    /// Mark all missed calls up to now as read
+    pub fn mark_read(&self, username: &str) -> DateTime<Utc> {
+        let now = Utc::now();
+        self.last_read
+            .lock()
+            .unwrap()
+            .insert(username.to_string(), now);
+        now
+    }
+
+    /// Time the user last marked missed calls as read
+    pub fn last_read(&self, username: &str) -> Option<DateTime<Utc>> {
+        self.last_read.lock().unwrap().get(username).copied()
+    }
+
+    /// Count missed calls newer than the user's last read marker
+    pub fn missed_since_last_read(&self, username: &str, entries: &[CallHistoryEntry]) -> usize {
+        let last_read = self.last_read(username);
+        entries
+            .iter()
+            .filter(|e| e.is_missed())
+            .filter(|e| last_read.is_none_or(|read| e.start_time > read))
+            .count()
+    }
+}
+
+impl Default for MissedCallTracker {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::domain::cdr::CallDirection;
+    use chrono::Duration;
+
+    fn leg(call_id: &str, caller: &str, callee: &str, offset_secs: i64) -> CallDetailRecord {
+        let mut cdr = CallDetailRecord::new(
+            call_id.to_string(),
+            caller.to_string(),
+            format!("sip:{}@example.com", caller),
+            "192.168.1.100".to_string(),
+            callee.to_string(),
+            format!("sip:{}@example.com", callee),
+            CallDirection::Internal,
+        );
+        cdr.start_time = Utc::now() - Duration::hours(1) + Duration::seconds(offset_secs);
+        cdr
+    }
+
+    fn answered(mut cdr: CallDetailRecord, talk_secs: i32) -> CallDetailRecord {
+        cdr.answer_time = Some(cdr.start_time + Duration::seconds(5));
+        cdr.call_duration = Some(talk_secs);
+        cdr.status = CallStatus::Completed;
+        cdr
+    }
+
+    fn ended(mut cdr: CallDetailRecord, status: CallStatus) -> CallDetailRecord {
+        cdr.status = status;
+        cdr
+    }
+
+    #[test]
+    fn test_missed_call_classification() {
+        let missed = ended(leg("c1", "alice", "bob", 0), CallStatus::NoAnswer);
+        let rejected = ended(leg("c2", "alice", "bob", 10), CallStatus::Rejected);
+        let talked = answered(leg("c3", "alice", "bob", 20), 30);
+
+        assert_eq!(
+            classify_leg(&missed, "bob"),
+            Some((HistoryDirection::Incoming, CallDisposition::Missed))
+        );
+        assert_eq!(
+            classify_leg(&rejected, "bob"),
+            Some((HistoryDirection::Incoming, CallDisposition::Rejected))
+        );
+        assert_eq!(
+            classify_leg(&talked, "bob"),
+            Some((HistoryDirection::Incoming, CallDisposition::Answered))
+        );
+
+        // The caller never "misses" their own outgoing call
+        assert_eq!(
+            classify_leg(&missed, "alice"),
+            Some((HistoryDirection::Outgoing, CallDisposition::Unanswered))
+        );
+        assert_eq!(classify_leg(&missed, "carol"), None);
+    }
+
+    #[test]
+    fn test_voicemail_classification() {
+        let mut cdr = answered(leg("c1", "alice", "bob", 0), 12);
+        cdr.end_reason = Some(END_REASON_VOICEMAIL.to_string());
+
+        assert_eq!(
+            classify_leg(&cdr, "bob"),
+            Some((HistoryDirection::Incoming, CallDisposition::Voicemail))
+        );
+    }
+
+    #[test]
+    fn test_forward_chain_grouping() {
+        // alice -> bob (no answer), forwarded alice -> carol (answered)
+        let mut first = ended(leg("c1", "alice", "bob", 0), CallStatus::NoAnswer);
+        first.set_correlation_id("chain-1".to_string());
+        let mut second = answered(leg("c2", "alice", "carol", 20), 45);
+        second.set_correlation_id("chain-1".to_string());
+        let unrelated = answered(leg("c3", "dave", "alice", 100), 10);
+
+        let cdrs = vec![unrelated, second, first];
+        let history = build_call_history("alice", &cdrs, 50);
+
+        assert_eq!(history.len(), 2);
+        // Newest first
+        assert_eq!(history[0].call_ids, vec!["c3".to_string()]);
+        assert_eq!(history[0].direction, HistoryDirection::Incoming);
+
+        let chain = &history[1];
+        assert_eq!(chain.call_ids, vec!["c1".to_string(), "c2".to_string()]);
+        assert_eq!(chain.direction, HistoryDirection::Outgoing);
+        assert_eq!(chain.remote_number, "bob");
+        assert_eq!(chain.disposition, CallDisposition::Answered);
+        assert_eq!(chain.duration, 45);
+
+        // From bob's side the forwarded call is still a missed call
+        let bob_history = build_call_history("bob", &cdrs, 50);
+        assert_eq!(bob_history.len(), 1);
+        assert_eq!(bob_history[0].disposition, CallDisposition::Missed);
+        assert_eq!(bob_history[0].remote_number, "alice");
+    }
+
+    #[test]
+    fn test_history_limit() {
+        let cdrs: Vec<_> = (0..10)
+            .map(|i| answered(leg(&format!("c{}", i), "alice", "bob", i), 1))
+            .collect();
+
+        let history = build_call_history("bob", &cdrs, 3);
+        assert_eq!(history.len(), 3);
+        assert_eq!(history[0].call_ids, vec!["c9".to_string()]);
+    }
+
+    #[test]
+    fn test_contact_name_matches_any_presentation() {
+        let plan = NumberingPlan::default();
+        let contacts = vec![
+            ("Dentist".to_string(), "(415) 555-1234".to_string()),
+            ("Reception".to_string(), "1001".to_string()),
+        ];
+
+        assert_eq!(
+            contact_name("+14155551234", &contacts, &plan, None),
+            Some("Dentist")
+        );
+        assert_eq!(
+            contact_name("14155551234", &contacts, &plan, None),
+            Some("Dentist")
+        );
+        assert_eq!(contact_name("1001", &contacts, &plan, None), Some("Reception"));
+        assert_eq!(contact_name("+14155551235", &contacts, &plan, None), None);
+    }
+
+    #[test]
+    fn test_missed_since_last_read_and_etag() {
+        let cdrs = vec![
+            ended(leg("c1", "alice", "bob", 0), CallStatus::NoAnswer),
+            ended(leg("c2", "carol", "bob", 10), CallStatus::Busy),
+        ];
+        let history = build_call_history("bob", &cdrs, 50);
+        let tracker = MissedCallTracker::new();
+
+        assert_eq!(tracker.missed_since_last_read("bob", &history), 2);
+        let etag_before = history_etag(&history, 2);
+        assert_eq!(etag_before, history_etag(&history, 2));
+
+        tracker.mark_read("bob");
+        assert_eq!(tracker.missed_since_last_read("bob", &history), 0);
+        assert_ne!(etag_before, history_etag(&history, 0));
+    }
+}
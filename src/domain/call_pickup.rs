@@ -42,6 +42,21 @@ impl PickupType {
             PickupType::Blf => "*11",        // *11<extension>
         }
     }
+
+    /// Whether the dial code is followed by an extension
+    pub fn takes_extension(&self) -> bool {
+        matches!(self, PickupType::Directed | PickupType::Blf)
+    }
+
+    /// All pickup types
+    pub fn all() -> [PickupType; 4] {
+        [
+            PickupType::Directed,
+            PickupType::Group,
+            PickupType::Any,
+            PickupType::Blf,
+        ]
+    }
 }
 
 /// Pickup group configuration
@@ -1,5 +1,6 @@
 /// Call Queue and ACD (Automatic Call Distribution) domain models
-use chrono::{DateTime, Utc};
+use crate::domain::call_forwarding::TimeRange;
+use chrono::{DateTime, Datelike, Utc};
 use serde::{Deserialize, Serialize};
 use std::collections::{HashMap, VecDeque};
 use std::time::Duration;
@@ -39,6 +40,75 @@ pub enum AgentStatus {
     LoggedOut,
 }
 
+/// Availability of an agent in a queue
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AgentAvailability {
+    Available,
+    Paused,
+    LoggedOut,
+}
+
+/// Why an agent's availability changed
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AgentStateReason {
+    /// Changed by the agent or a supervisor
+    Manual,
+    /// All of the agent's registrations expired
+    RegistrationExpired,
+    /// The agent's device stopped answering keepalives
+    KeepaliveFailed,
+    /// The agent did not answer the last offered calls
+    MissedOffers,
+    /// The agent enabled DND
+    DndEnabled,
+    /// The agent disabled DND
+    DndDisabled,
+}
+
+impl AgentStateReason {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Manual => "manual",
+            Self::RegistrationExpired => "registration_expired",
+            Self::KeepaliveFailed => "keepalive_failed",
+            Self::MissedOffers => "missed_offers",
+            Self::DndEnabled => "dnd_enabled",
+            Self::DndDisabled => "dnd_disabled",
+        }
+    }
+
+    /// Whether the change was made without the agent or a supervisor
+    pub fn is_automatic(&self) -> bool {
+        !matches!(self, Self::Manual)
+    }
+}
+
+/// What happens to an agent who stops responding
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum InactivityAction {
+    /// Pause the agent in the queue
+    #[default]
+    Pause,
+    /// Log the agent out of the queue
+    LogOut,
+}
+
+/// Change of an agent's availability in one queue
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AgentStateChange {
+    pub queue_id: Uuid,
+    pub member_id: Uuid,
+    /// User ID of the agent
+    pub agent_id: i32,
+    pub username: String,
+    pub from: AgentAvailability,
+    pub to: AgentAvailability,
+    pub reason: AgentStateReason,
+    pub occurred_at: DateTime<Utc>,
+}
+
 /// Queue member (agent) in a call queue
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct QueueMember {
@@ -56,6 +126,12 @@ pub struct QueueMember {
     pub missed_calls: u64,
     pub total_talk_time: Duration,
     pub joined_at: DateTime<Utc>,
+    /// Offers not answered since the agent last answered one
+    #[serde(default)]
+    pub consecutive_missed: u32,
+    /// Pause in every queue while DND is on
+    #[serde(default)]
+    pub dnd_linked: bool,
 }
 
 impl QueueMember {
@@ -75,6 +151,8 @@ impl QueueMember {
             missed_calls: 0,
             total_talk_time: Duration::from_secs(0),
             joined_at: Utc::now(),
+            consecutive_missed: 0,
+            dnd_linked: false,
         }
     }
 
@@ -105,18 +183,31 @@ impl QueueMember {
         self.paused_reason = None;
     }
 
+    /// Availability, as shown in the state history
+    pub fn availability(&self) -> AgentAvailability {
+        if self.status == AgentStatus::LoggedOut {
+            AgentAvailability::LoggedOut
+        } else if self.paused {
+            AgentAvailability::Paused
+        } else {
+            AgentAvailability::Available
+        }
+    }
+
     /// Record call answered
     pub fn record_answered(&mut self, talk_time: Duration) {
         self.total_calls += 1;
         self.answered_calls += 1;
         self.total_talk_time += talk_time;
         self.last_call_time = Some(Utc::now());
+        self.consecutive_missed = 0;
     }
 
     /// Record call missed
     pub fn record_missed(&mut self) {
         self.total_calls += 1;
         self.missed_calls += 1;
+        self.consecutive_missed += 1;
     }
 }
 
@@ -132,6 +223,8 @@ pub struct QueuedCall {
     pub position: usize,
     pub wait_time: Duration,
     pub priority: u32,
+    /// Set while the caller's place is held for a callback
+    pub callback_id: Option<Uuid>,
 }
 
 impl QueuedCall {
@@ -146,6 +239,7 @@ impl QueuedCall {
             position: 0,
             wait_time: Duration::from_secs(0),
             priority: 0,
+            callback_id: None,
         }
     }
 
@@ -177,10 +271,49 @@ pub struct CallQueue {
     pub periodic_announce_frequency: Duration,
     pub overflow_queue_id: Option<Uuid>,
     pub overflow_action: OverflowAction,
+    /// Calls answered within this wait count towards the service level
+    #[serde(default = "default_service_level_threshold")]
+    pub service_level_threshold: Duration,
+    /// DTMF digit a waiting caller presses to be called back (disabled if unset)
+    #[serde(default)]
+    pub callback_digit: Option<char>,
+    /// Calls placed to a callback number before the callback is abandoned
+    #[serde(default = "default_callback_max_attempts")]
+    pub callback_max_attempts: u32,
+    /// Delay between callback attempts
+    #[serde(default = "default_callback_retry_delay")]
+    pub callback_retry_delay: Duration,
+    /// Unanswered offers in a row after which an agent stops receiving
+    /// calls (0: never)
+    #[serde(default = "default_max_missed_offers")]
+    pub max_missed_offers: u32,
+    /// What happens to an agent who stops responding
+    #[serde(default)]
+    pub inactivity_action: InactivityAction,
+    /// Rules sending calls elsewhere (e.g. an answering service), checked
+    /// in order when a call arrives and while it waits
+    #[serde(default)]
+    pub overflow_rules: Vec<OverflowRule>,
     pub created_at: DateTime<Utc>,
     pub updated_at: DateTime<Utc>,
 }
 
+fn default_service_level_threshold() -> Duration {
+    Duration::from_secs(20)
+}
+
+fn default_callback_max_attempts() -> u32 {
+    3
+}
+
+fn default_callback_retry_delay() -> Duration {
+    Duration::from_secs(60)
+}
+
+fn default_max_missed_offers() -> u32 {
+    3
+}
+
 /// Action to take when queue overflows
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
 pub enum OverflowAction {
@@ -196,6 +329,79 @@ pub enum OverflowAction {
     Announcement,
 }
 
+/// Condition of an overflow rule
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum OverflowCondition {
+    /// Outside the hours the queue is staffed (UTC)
+    OutsideHours { hours: TimeRange },
+    /// More than `count` callers waiting; the callers behind the first
+    /// `count` overflow
+    CallsWaitingAbove { count: usize },
+    /// The caller has waited longer than `secs`
+    WaitAbove { secs: u64 },
+    /// No agent logged in (paused agents count as logged in)
+    NoAgentsLoggedIn,
+}
+
+/// Where overflowed calls are sent
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum OverflowDestination {
+    /// External number, e.g. an answering service, dialed through a trunk
+    External {
+        number: String,
+        /// Trunk to use (the outbound route's when unset)
+        #[serde(default)]
+        trunk_id: Option<Uuid>,
+    },
+    /// Another queue
+    Queue { queue_id: Uuid },
+    /// A voicemail box
+    Voicemail { mailbox: String },
+}
+
+impl std::fmt::Display for OverflowDestination {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::External { number, .. } => write!(f, "external number {}", number),
+            Self::Queue { queue_id } => write!(f, "queue {}", queue_id),
+            Self::Voicemail { mailbox } => write!(f, "voicemail {}", mailbox),
+        }
+    }
+}
+
+/// Overflow rule of a queue
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct OverflowRule {
+    pub condition: OverflowCondition,
+    pub destination: OverflowDestination,
+    /// Announcement played to the caller before the transfer
+    #[serde(default)]
+    pub announcement: Option<String>,
+}
+
+/// A caller's situation when the overflow rules are checked
+#[derive(Debug, Clone, Copy)]
+pub struct OverflowCheck {
+    pub now: DateTime<Utc>,
+    /// Place in line, starting at 1
+    pub position: usize,
+    pub wait: Duration,
+    pub agents_logged_in: usize,
+}
+
+impl OverflowCondition {
+    pub fn matches(&self, check: &OverflowCheck) -> bool {
+        match self {
+            Self::OutsideHours { hours } => !hours.contains(check.now.time(), check.now.weekday()),
+            Self::CallsWaitingAbove { count } => check.position > *count,
+            Self::WaitAbove { secs } => check.wait > Duration::from_secs(*secs),
+            Self::NoAgentsLoggedIn => check.agents_logged_in == 0,
+        }
+    }
+}
+
 impl CallQueue {
     pub fn new(name: String, extension: String, strategy: QueueStrategy) -> Self {
         let now = Utc::now();
@@ -217,10 +423,94 @@ impl CallQueue {
             periodic_announce_frequency: Duration::from_secs(60),
             overflow_queue_id: None,
             overflow_action: OverflowAction::Busy,
+            service_level_threshold: default_service_level_threshold(),
+            callback_digit: None,
+            callback_max_attempts: default_callback_max_attempts(),
+            callback_retry_delay: default_callback_retry_delay(),
+            max_missed_offers: default_max_missed_offers(),
+            inactivity_action: InactivityAction::Pause,
+            overflow_rules: Vec::new(),
             created_at: now,
             updated_at: now,
         }
     }
+
+    /// First overflow rule applying to a caller
+    pub fn overflow_rule(&self, check: &OverflowCheck) -> Option<&OverflowRule> {
+        self.overflow_rules
+            .iter()
+            .find(|rule| rule.condition.matches(check))
+    }
+}
+
+/// State of a queue callback
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum CallbackStatus {
+    /// Holding the caller's place in line
+    Pending,
+    /// Calling the caller back
+    Dialing,
+    /// Caller was reached and connected to an agent
+    Connected,
+    /// Every attempt failed
+    Abandoned,
+    /// Cancelled before the caller was reached
+    Cancelled,
+}
+
+/// Caller who left the queue to be called back
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct QueueCallback {
+    pub id: Uuid,
+    pub queue_id: Uuid,
+    /// Call-ID of the call that requested the callback
+    pub original_call_id: String,
+    /// Number to call back
+    pub number: String,
+    pub caller_name: Option<String>,
+    /// When the caller originally joined the queue
+    pub enqueued_at: DateTime<Utc>,
+    pub requested_at: DateTime<Utc>,
+    pub status: CallbackStatus,
+    pub attempts: u32,
+    pub max_attempts: u32,
+    /// Earliest time of the next attempt after a failed one
+    pub next_attempt_at: Option<DateTime<Utc>>,
+    pub last_failure: Option<String>,
+    /// Call-IDs of the calls placed to `number`
+    pub attempt_call_ids: Vec<String>,
+}
+
+impl QueueCallback {
+    pub fn new(call: &QueuedCall, number: String, max_attempts: u32) -> Self {
+        Self {
+            id: Uuid::new_v4(),
+            queue_id: call.queue_id,
+            original_call_id: call.call_id.clone(),
+            number,
+            caller_name: call.caller_name.clone(),
+            enqueued_at: call.enqueued_at,
+            requested_at: Utc::now(),
+            status: CallbackStatus::Pending,
+            attempts: 0,
+            max_attempts: max_attempts.max(1),
+            next_attempt_at: None,
+            last_failure: None,
+            attempt_call_ids: Vec::new(),
+        }
+    }
+
+    /// Whether the callback may be dialed at `now`
+    pub fn is_due(&self, now: DateTime<Utc>) -> bool {
+        self.status == CallbackStatus::Pending
+            && self.next_attempt_at.is_none_or(|at| at <= now)
+    }
+
+    /// Correlation ID linking the CDRs of the original call and every attempt
+    pub fn correlation_id(&self) -> String {
+        format!("callback-{}", self.id)
+    }
 }
 
 /// Call queue state (runtime data)
@@ -490,6 +780,60 @@ mod tests {
         assert_eq!(queue.max_queue_size, 100);
     }
 
+    #[test]
+    fn test_overflow_rules_in_order() {
+        let mut queue = CallQueue::new(
+            "Support Queue".to_string(),
+            "8000".to_string(),
+            QueueStrategy::RoundRobin,
+        );
+        let service = OverflowDestination::External {
+            number: "+15550199".to_string(),
+            trunk_id: None,
+        };
+        queue.overflow_rules = vec![
+            OverflowRule {
+                condition: OverflowCondition::OutsideHours {
+                    hours: TimeRange::business_hours(),
+                },
+                destination: service.clone(),
+                announcement: Some("office_closed.wav".to_string()),
+            },
+            OverflowRule {
+                condition: OverflowCondition::CallsWaitingAbove { count: 2 },
+                destination: OverflowDestination::Voicemail {
+                    mailbox: "8000".to_string(),
+                },
+                announcement: None,
+            },
+        ];
+
+        // Tuesday 10:00 UTC, third in line
+        let tuesday = DateTime::parse_from_rfc3339("2025-11-04T10:00:00Z")
+            .unwrap()
+            .with_timezone(&Utc);
+        let mut check = OverflowCheck {
+            now: tuesday,
+            position: 2,
+            wait: Duration::from_secs(0),
+            agents_logged_in: 1,
+        };
+        assert!(queue.overflow_rule(&check).is_none());
+        check.position = 3;
+        assert!(matches!(
+            queue.overflow_rule(&check).unwrap().destination,
+            OverflowDestination::Voicemail { .. }
+        ));
+
+        // Saturday: the hours rule comes first
+        check.now = tuesday + chrono::Duration::days(4);
+        assert_eq!(queue.overflow_rule(&check).unwrap().destination, service);
+
+        let json = serde_json::to_value(&queue.overflow_rules[1]).unwrap();
+        assert_eq!(json["condition"], serde_json::json!({"type": "calls_waiting_above", "count": 2}));
+        assert_eq!(json["destination"]["type"], "voicemail");
+    }
+
     #[test]
     fn test_queue_state_enqueue() {
         let queue_id = Uuid::new_v4();
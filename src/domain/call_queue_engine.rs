@@ -1,12 +1,17 @@
 /// Call Queue Engine for managing queued calls and agent distribution
 use crate::domain::call_queue::*;
 use crate::domain::audio::{AudioFileManager, StreamingAudioPlayer, SequenceBuilder, PlaybackOptions};
+use crate::domain::queue_reporting::{AgentStateCounts, QueueEvent, QueueEventType, QueueSnapshot};
 use std::collections::{HashMap, VecDeque};
 use std::sync::{Arc, Mutex};
 use std::time::Duration;
 use chrono::{DateTime, Utc};
 use uuid::Uuid;
 use rand::Rng;
+use tokio::sync::{broadcast, mpsc};
+
+/// Agent state changes kept per agent for the state history
+const STATE_HISTORY_LIMIT: usize = 100;
 
 /// Call queue engine error
 #[derive(Debug, Clone, PartialEq, Eq)]
@@ -21,6 +26,8 @@ pub enum QueueEngineError {
     CallNotFound,
     /// Member not found
     MemberNotFound,
+    /// Callback not found
+    CallbackNotFound,
     /// Invalid operation
     InvalidOperation(String),
 }
@@ -33,6 +40,7 @@ impl std::fmt::Display for QueueEngineError {
             Self::NoAvailableAgents => write!(f, "No available agents"),
             Self::CallNotFound => write!(f, "Call not found"),
             Self::MemberNotFound => write!(f, "Member not found"),
+            Self::CallbackNotFound => write!(f, "Callback not found"),
             Self::InvalidOperation(msg) => write!(f, "Invalid operation: {}", msg),
         }
     }
@@ -91,14 +99,25 @@ impl QueueStatistics {
     }
 }
 
+/// Outcome of a call arriving at a queue
+#[derive(Debug, Clone)]
+pub enum QueueArrival {
+    /// The call waits for an agent
+    Queued(QueuedCall),
+    /// An overflow rule applied: the call goes to its destination instead
+    Overflowed(OverflowRule),
+}
+
 /// Active queue session
 struct QueueSession {
     /// Queue configuration
     queue: CallQueue,
     /// Queue members
     members: HashMap<Uuid, QueueMember>,
-    /// Waiting calls
+    /// Waiting calls, including places held for callbacks
     waiting_calls: VecDeque<QueuedCall>,
+    /// Callbacks holding a place in `waiting_calls`
+    callbacks: HashMap<Uuid, QueueCallback>,
     /// Active calls (call_id -> agent_id)
     active_calls: HashMap<String, Uuid>,
     /// Round-robin position for round-robin strategy
@@ -111,17 +130,71 @@ struct QueueSession {
 
 impl QueueSession {
     fn new(queue: CallQueue) -> Self {
+        let statistics = QueueStatistics {
+            service_level_threshold: queue.service_level_threshold,
+            ..QueueStatistics::default()
+        };
         Self {
             queue,
             members: HashMap::new(),
             waiting_calls: VecDeque::new(),
+            callbacks: HashMap::new(),
             active_calls: HashMap::new(),
             round_robin_position: 0,
-            statistics: QueueStatistics::default(),
+            statistics,
             answered_within_threshold: 0,
         }
     }
 
+    /// User ID of a member, for queue events
+    fn agent_user_id(&self, member_id: Uuid) -> Option<i32> {
+        self.members.get(&member_id).map(|m| m.user_id)
+    }
+
+    /// Wallboard view of the queue
+    fn snapshot(&self) -> QueueSnapshot {
+        let mut agents = AgentStateCounts::default();
+        for member in self.members.values() {
+            if member.paused {
+                agents.paused += 1;
+                continue;
+            }
+            match member.status {
+                AgentStatus::Available => agents.available += 1,
+                AgentStatus::Busy => agents.busy += 1,
+                AgentStatus::AfterCallWork => agents.after_call_work += 1,
+                AgentStatus::Paused => agents.paused += 1,
+                AgentStatus::LoggedOut => agents.logged_out += 1,
+            }
+        }
+
+        let now = Utc::now();
+        let longest_wait_secs = self
+            .waiting_calls
+            .iter()
+            .map(|c| (now - c.enqueued_at).num_seconds().max(0) as u64)
+            .max()
+            .unwrap_or(0);
+
+        QueueSnapshot {
+            queue_id: self.queue.id,
+            name: self.queue.name.clone(),
+            calls_waiting: self.waiting_calls.len(),
+            calls_active: self.active_calls.len(),
+            longest_wait_secs,
+            agents,
+            taken_at: now,
+        }
+    }
+
+    /// Members logged in to the queue, paused ones included
+    fn agents_logged_in(&self) -> usize {
+        self.members
+            .values()
+            .filter(|m| m.status != AgentStatus::LoggedOut)
+            .count()
+    }
+
     /// Get available members
     fn available_members(&self) -> Vec<&QueueMember> {
         self.members
@@ -188,6 +261,85 @@ impl QueueSession {
         }
     }
 
+    /// Take an agent that stopped responding out of the queue
+    ///
+    /// Agents already paused are left alone when pausing; logged out agents
+    /// always are.
+    fn apply_inactivity(
+        &mut self,
+        member_id: Uuid,
+        action: InactivityAction,
+        reason: AgentStateReason,
+    ) -> Option<AgentStateChange> {
+        let queue_id = self.queue.id;
+        let member = self.members.get_mut(&member_id)?;
+        let to = match action {
+            InactivityAction::Pause => AgentAvailability::Paused,
+            InactivityAction::LogOut => AgentAvailability::LoggedOut,
+        };
+        match member.availability() {
+            AgentAvailability::LoggedOut => None,
+            current if current == to => None,
+            _ => Some(set_availability(queue_id, member, to, reason)),
+        }
+    }
+
+    /// Index of a caller still on the line (not a place held for a callback)
+    fn waiting_index(&self, call_id: &str) -> Option<usize> {
+        self.waiting_calls
+            .iter()
+            .position(|c| c.call_id == call_id && c.callback_id.is_none())
+    }
+
+    /// Index of the place held for a callback
+    fn callback_index(&self, callback_id: Uuid) -> Option<usize> {
+        self.waiting_calls
+            .iter()
+            .position(|c| c.callback_id == Some(callback_id))
+    }
+
+    /// Hand the waiting entry at `index` to an agent as call `call_id`
+    fn connect_waiting(&mut self, index: usize, call_id: &str, agent_id: Uuid) -> QueuedCall {
+        let mut queued_call = self.waiting_calls.remove(index).unwrap();
+        queued_call.update_wait_time();
+
+        // Update statistics
+        self.statistics.calls_answered += 1;
+        self.statistics.calls_active += 1;
+
+        // Check if answered within SLA threshold
+        if queued_call.wait_time <= self.statistics.service_level_threshold {
+            self.answered_within_threshold += 1;
+        }
+
+        // Update average wait time
+        if self.statistics.calls_answered > 0 {
+            let total_wait = self.statistics.avg_wait_time.as_secs() * (self.statistics.calls_answered - 1)
+                + queued_call.wait_time.as_secs();
+            self.statistics.avg_wait_time = Duration::from_secs(total_wait / self.statistics.calls_answered);
+        }
+
+        // Update longest wait time
+        if queued_call.wait_time > self.statistics.longest_wait_time {
+            self.statistics.longest_wait_time = queued_call.wait_time;
+        }
+
+        // Mark agent as busy
+        if let Some(agent) = self.members.get_mut(&agent_id) {
+            agent.mark_busy();
+            agent.consecutive_missed = 0;
+        }
+
+        // Track active call
+        self.active_calls.insert(call_id.to_string(), agent_id);
+        self.update_positions();
+
+        // Recalculate service level
+        self.statistics.calculate_service_level(self.answered_within_threshold);
+
+        queued_call
+    }
+
     /// Update queue positions
     fn update_positions(&mut self) {
         for (index, call) in self.waiting_calls.iter_mut().enumerate() {
@@ -216,12 +368,57 @@ impl QueueSession {
     }
 }
 
+/// Change a member's availability, returning the change
+fn set_availability(
+    queue_id: Uuid,
+    member: &mut QueueMember,
+    to: AgentAvailability,
+    reason: AgentStateReason,
+) -> AgentStateChange {
+    let from = member.availability();
+    match to {
+        AgentAvailability::Available => {
+            member.unpause();
+            if member.status == AgentStatus::LoggedOut {
+                member.mark_available();
+            }
+        }
+        AgentAvailability::Paused => {
+            if member.status == AgentStatus::LoggedOut {
+                member.mark_available();
+            }
+            member.pause(Some(reason.as_str().to_string()));
+        }
+        AgentAvailability::LoggedOut => {
+            member.unpause();
+            member.status = AgentStatus::LoggedOut;
+        }
+    }
+
+    AgentStateChange {
+        queue_id,
+        member_id: member.id,
+        agent_id: member.user_id,
+        username: member.username.clone(),
+        from,
+        to,
+        reason,
+        occurred_at: Utc::now(),
+    }
+}
+
 /// Call Queue Engine
 pub struct CallQueueEngine {
     /// Active queue sessions
     sessions: Arc<Mutex<HashMap<Uuid, QueueSession>>>,
     /// Audio file manager for announcements
     audio_manager: Option<Arc<AudioFileManager>>,
+    /// Receives queue events for reporting
+    event_sink: Option<mpsc::UnboundedSender<QueueEvent>>,
+    /// Agent availability changes
+    agent_states: broadcast::Sender<AgentStateChange>,
+    /// Recent availability changes by agent user ID, oldest first
+    state_history: Mutex<HashMap<i32, VecDeque<AgentStateChange>>>,
 }
 
 impl CallQueueEngine {
@@ -230,6 +427,9 @@ impl CallQueueEngine {
         Self {
             sessions: Arc::new(Mutex::new(HashMap::new())),
             audio_manager: None,
+            event_sink: None,
+            agent_states: broadcast::channel(256).0,
+            state_history: Mutex::new(HashMap::new()),
         }
     }
 
@@ -238,12 +438,73 @@ impl CallQueueEngine {
         self.audio_manager = Some(manager);
     }
 
+    /// Send queue events to `sink` (see `spawn_queue_event_recorder`)
+    pub fn set_event_sink(&mut self, sink: mpsc::UnboundedSender<QueueEvent>) {
+        self.event_sink = Some(sink);
+    }
+
+    fn emit(&self, event: QueueEvent) {
+        if let Some(ref sink) = self.event_sink {
+            // Reporting must never hold up call handling
+            let _ = sink.send(event);
+        }
+    }
+
+    /// Subscribe to agent availability changes
+    pub fn subscribe_agent_states(&self) -> broadcast::Receiver<AgentStateChange> {
+        self.agent_states.subscribe()
+    }
+
+    /// Recent availability changes of an agent, oldest first
+    pub fn agent_state_history(&self, agent_id: i32) -> Vec<AgentStateChange> {
+        let history = self.state_history.lock().unwrap();
+        history
+            .get(&agent_id)
+            .map(|changes| changes.iter().cloned().collect())
+            .unwrap_or_default()
+    }
+
+    fn record_state_changes(&self, changes: &[AgentStateChange]) {
+        let mut history = self.state_history.lock().unwrap();
+        for change in changes {
+            let entries = history.entry(change.agent_id).or_default();
+            if entries.len() == STATE_HISTORY_LIMIT {
+                entries.pop_front();
+            }
+            entries.push_back(change.clone());
+            // Nobody listening is fine
+            let _ = self.agent_states.send(change.clone());
+        }
+    }
+
     /// Start a queue session
     pub fn start_queue(&self, queue: CallQueue) {
         let mut sessions = self.sessions.lock().unwrap();
         sessions.insert(queue.id, QueueSession::new(queue));
     }
 
+    /// Configuration of a running queue
+    pub fn get_queue(&self, queue_id: Uuid) -> Result<CallQueue, QueueEngineError> {
+        let sessions = self.sessions.lock().unwrap();
+        sessions
+            .get(&queue_id)
+            .map(|session| session.queue.clone())
+            .ok_or(QueueEngineError::QueueNotFound)
+    }
+
+    /// Member of a running queue
+    pub fn get_member(&self, queue_id: Uuid, member_id: Uuid) -> Result<QueueMember, QueueEngineError> {
+        let sessions = self.sessions.lock().unwrap();
+        let session = sessions
+            .get(&queue_id)
+            .ok_or(QueueEngineError::QueueNotFound)?;
+        session
+            .members
+            .get(&member_id)
+            .cloned()
+            .ok_or(QueueEngineError::MemberNotFound)
+    }
+
     /// Stop a queue session
     pub fn stop_queue(&self, queue_id: Uuid) {
         let mut sessions = self.sessions.lock().unwrap();
@@ -285,14 +546,64 @@ impl CallQueueEngine {
             .get_mut(&queue_id)
             .ok_or(QueueEngineError::QueueNotFound)?;
 
+        self.push_call(session, call_id, caller, caller_name, Utc::now())
+    }
+
+    /// Enqueue a call arriving at `now`, unless one of the queue's overflow
+    /// rules sends it elsewhere
+    pub fn arrive(
+        &self,
+        queue_id: Uuid,
+        call_id: String,
+        caller: String,
+        caller_name: Option<String>,
+        now: DateTime<Utc>,
+    ) -> Result<QueueArrival, QueueEngineError> {
+        let mut sessions = self.sessions.lock().unwrap();
+        let session = sessions
+            .get_mut(&queue_id)
+            .ok_or(QueueEngineError::QueueNotFound)?;
+
+        let check = OverflowCheck {
+            now,
+            position: session.waiting_calls.len() + 1,
+            wait: Duration::from_secs(0),
+            agents_logged_in: session.agents_logged_in(),
+        };
+        if let Some(rule) = session.queue.overflow_rule(&check).cloned() {
+            session.statistics.total_calls += 1;
+            session.statistics.calls_overflowed += 1;
+            self.emit(
+                QueueEvent::new(queue_id, call_id, QueueEventType::Overflowed).with_occurred_at(now),
+            );
+            return Ok(QueueArrival::Overflowed(rule));
+        }
+
+        self.push_call(session, call_id, caller, caller_name, now)
+            .map(QueueArrival::Queued)
+    }
+
+    /// Add a call to the back of a queue
+    fn push_call(
+        &self,
+        session: &mut QueueSession,
+        call_id: String,
+        caller: String,
+        caller_name: Option<String>,
+        enqueued_at: DateTime<Utc>,
+    ) -> Result<QueuedCall, QueueEngineError> {
+        let queue_id = session.queue.id;
+
         // Check if queue is full
         if session.is_full() {
             session.statistics.calls_overflowed += 1;
+            self.emit(QueueEvent::new(queue_id, call_id, QueueEventType::Overflowed));
             return Err(QueueEngineError::QueueFull);
         }
 
         // Create queued call
         let mut queued_call = QueuedCall::new(call_id, caller, queue_id);
+        queued_call.enqueued_at = enqueued_at;
         if let Some(name) = caller_name {
             queued_call.caller_name = Some(name);
         }
@@ -302,6 +613,11 @@ impl CallQueueEngine {
         session.statistics.total_calls += 1;
         session.update_positions();
 
+        self.emit(
+            QueueEvent::new(queue_id, queued_call.call_id.clone(), QueueEventType::Enqueued)
+                .with_occurred_at(queued_call.enqueued_at),
+        );
+
         Ok(queued_call)
     }
 
@@ -316,59 +632,172 @@ impl CallQueueEngine {
         Ok(agent_id.and_then(|id| session.members.get(&id).cloned()))
     }
 
-    /// Connect call to agent
-    pub fn connect_call(
+    /// Record that a waiting call is ringing an agent
+    pub fn offer_call(
         &self,
         queue_id: Uuid,
         call_id: &str,
         agent_id: Uuid,
     ) -> Result<(), QueueEngineError> {
+        let sessions = self.sessions.lock().unwrap();
+        let session = sessions
+            .get(&queue_id)
+            .ok_or(QueueEngineError::QueueNotFound)?;
+
+        if session.waiting_index(call_id).is_none() {
+            return Err(QueueEngineError::CallNotFound);
+        }
+        let user_id = session
+            .agent_user_id(agent_id)
+            .ok_or(QueueEngineError::MemberNotFound)?;
+
+        self.emit(QueueEvent::new(queue_id, call_id, QueueEventType::Offered).with_agent(user_id));
+        Ok(())
+    }
+
+    /// An agent did not answer a call offered to it
+    ///
+    /// After the queue's `max_missed_offers` unanswered offers in a row the
+    /// agent is paused or logged out, per the queue's `inactivity_action`.
+    pub fn offer_missed(
+        &self,
+        queue_id: Uuid,
+        agent_id: Uuid,
+    ) -> Result<Option<AgentStateChange>, QueueEngineError> {
         let mut sessions = self.sessions.lock().unwrap();
         let session = sessions
             .get_mut(&queue_id)
             .ok_or(QueueEngineError::QueueNotFound)?;
 
-        // Remove from waiting queue
-        let call_index = session
-            .waiting_calls
-            .iter()
-            .position(|c| c.call_id == call_id)
-            .ok_or(QueueEngineError::CallNotFound)?;
-
-        let queued_call = session.waiting_calls.remove(call_index).unwrap();
+        let max_missed = session.queue.max_missed_offers;
+        let action = session.queue.inactivity_action;
+        let agent = session
+            .members
+            .get_mut(&agent_id)
+            .ok_or(QueueEngineError::MemberNotFound)?;
+        agent.record_missed();
 
-        // Update statistics
-        session.statistics.calls_answered += 1;
-        session.statistics.calls_active += 1;
+        if max_missed == 0 || agent.consecutive_missed < max_missed {
+            return Ok(None);
+        }
+        let change = session.apply_inactivity(agent_id, action, AgentStateReason::MissedOffers);
+        drop(sessions);
 
-        // Check if answered within SLA threshold
-        if queued_call.wait_time <= session.statistics.service_level_threshold {
-            session.answered_within_threshold += 1;
+        if let Some(ref change) = change {
+            self.record_state_changes(std::slice::from_ref(change));
         }
+        Ok(change)
+    }
 
-        // Update average wait time
-        if session.statistics.calls_answered > 0 {
-            let total_wait = session.statistics.avg_wait_time.as_secs() * (session.statistics.calls_answered - 1)
-                + queued_call.wait_time.as_secs();
-            session.statistics.avg_wait_time = Duration::from_secs(total_wait / session.statistics.calls_answered);
+    /// Pause or log out an agent in every queue because its device is no
+    /// longer reachable (registration expired, keepalives failing)
+    pub fn agent_unreachable(&self, username: &str, reason: AgentStateReason) -> Vec<AgentStateChange> {
+        let mut changes = Vec::new();
+        {
+            let mut sessions = self.sessions.lock().unwrap();
+            for session in sessions.values_mut() {
+                let action = session.queue.inactivity_action;
+                let member_ids: Vec<Uuid> = session
+                    .members
+                    .values()
+                    .filter(|m| m.username == username)
+                    .map(|m| m.id)
+                    .collect();
+                for member_id in member_ids {
+                    changes.extend(session.apply_inactivity(member_id, action, reason));
+                }
+            }
         }
+        self.record_state_changes(&changes);
+        changes
+    }
 
-        // Update longest wait time
-        if queued_call.wait_time > session.statistics.longest_wait_time {
-            session.statistics.longest_wait_time = queued_call.wait_time;
+    /// Pause (or resume) a DND-linked agent in every queue when it turns
+    /// DND on (or off)
+    ///
+    /// Only pauses made for DND are resumed.
+    pub fn set_agent_dnd(&self, username: &str, enabled: bool) -> Vec<AgentStateChange> {
+        let mut changes = Vec::new();
+        {
+            let mut sessions = self.sessions.lock().unwrap();
+            for session in sessions.values_mut() {
+                let queue_id = session.queue.id;
+                for member in session.members.values_mut() {
+                    if !member.dnd_linked || member.username != username {
+                        continue;
+                    }
+                    let change = if enabled {
+                        (member.availability() == AgentAvailability::Available).then(|| {
+                            set_availability(queue_id, member, AgentAvailability::Paused, AgentStateReason::DndEnabled)
+                        })
+                    } else {
+                        let paused_for_dnd = member.availability() == AgentAvailability::Paused
+                            && member.paused_reason.as_deref() == Some(AgentStateReason::DndEnabled.as_str());
+                        paused_for_dnd.then(|| {
+                            set_availability(queue_id, member, AgentAvailability::Available, AgentStateReason::DndDisabled)
+                        })
+                    };
+                    changes.extend(change);
+                }
+            }
         }
+        self.record_state_changes(&changes);
+        changes
+    }
 
-        // Mark agent as busy
-        if let Some(agent) = session.members.get_mut(&agent_id) {
-            agent.mark_busy();
+    /// Pause, resume or log out an agent in a queue
+    ///
+    /// Returns None when the agent already has that availability.
+    pub fn set_agent_availability(
+        &self,
+        queue_id: Uuid,
+        member_id: Uuid,
+        to: AgentAvailability,
+        reason: AgentStateReason,
+    ) -> Result<Option<AgentStateChange>, QueueEngineError> {
+        let mut sessions = self.sessions.lock().unwrap();
+        let session = sessions
+            .get_mut(&queue_id)
+            .ok_or(QueueEngineError::QueueNotFound)?;
+        let member = session
+            .members
+            .get_mut(&member_id)
+            .ok_or(QueueEngineError::MemberNotFound)?;
+
+        if member.availability() == to {
+            return Ok(None);
         }
+        let change = set_availability(queue_id, member, to, reason);
+        drop(sessions);
 
-        // Track active call
-        session.active_calls.insert(call_id.to_string(), agent_id);
-        session.update_positions();
+        self.record_state_changes(std::slice::from_ref(&change));
+        Ok(Some(change))
+    }
 
-        // Recalculate service level
-        session.statistics.calculate_service_level(session.answered_within_threshold);
+    /// Connect call to agent
+    pub fn connect_call(
+        &self,
+        queue_id: Uuid,
+        call_id: &str,
+        agent_id: Uuid,
+    ) -> Result<(), QueueEngineError> {
+        let mut sessions = self.sessions.lock().unwrap();
+        let session = sessions
+            .get_mut(&queue_id)
+            .ok_or(QueueEngineError::QueueNotFound)?;
+
+        // Remove from waiting queue
+        let call_index = session
+            .waiting_index(call_id)
+            .ok_or(QueueEngineError::CallNotFound)?;
+        let queued_call = session.connect_waiting(call_index, call_id, agent_id);
+
+        let mut event = QueueEvent::new(queue_id, call_id, QueueEventType::Answered)
+            .with_wait(queued_call.wait_time);
+        if let Some(user_id) = session.agent_user_id(agent_id) {
+            event = event.with_agent(user_id);
+        }
+        self.emit(event);
 
         Ok(())
     }
@@ -399,6 +828,14 @@ impl CallQueueEngine {
 
         session.statistics.calls_active = session.statistics.calls_active.saturating_sub(1);
 
+        let mut event = QueueEvent::new(queue_id, call_id, QueueEventType::Completed)
+            .with_talk(talk_time)
+            .with_wrap_up(session.queue.wrap_up_time);
+        if let Some(user_id) = session.agent_user_id(agent_id) {
+            event = event.with_agent(user_id);
+        }
+        self.emit(event);
+
         Ok(())
     }
 
@@ -411,18 +848,340 @@ impl CallQueueEngine {
 
         // Remove from waiting queue
         let call_index = session
-            .waiting_calls
-            .iter()
-            .position(|c| c.call_id == call_id)
+            .waiting_index(call_id)
             .ok_or(QueueEngineError::CallNotFound)?;
 
-        session.waiting_calls.remove(call_index);
+        let mut queued_call = session.waiting_calls.remove(call_index).unwrap();
+        queued_call.update_wait_time();
         session.statistics.calls_abandoned += 1;
         session.update_positions();
 
+        self.emit(
+            QueueEvent::new(queue_id, call_id, QueueEventType::Abandoned)
+                .with_wait(queued_call.wait_time),
+        );
+
         Ok(())
     }
 
+    /// Take a waiting call out of the queue for the overflow action
+    /// (e.g. after `max_wait_time`)
+    pub fn overflow_call(&self, queue_id: Uuid, call_id: &str) -> Result<QueuedCall, QueueEngineError> {
+        let mut sessions = self.sessions.lock().unwrap();
+        let session = sessions
+            .get_mut(&queue_id)
+            .ok_or(QueueEngineError::QueueNotFound)?;
+
+        let call_index = session
+            .waiting_index(call_id)
+            .ok_or(QueueEngineError::CallNotFound)?;
+
+        let mut queued_call = session.waiting_calls.remove(call_index).unwrap();
+        queued_call.update_wait_time();
+        session.statistics.calls_overflowed += 1;
+        session.update_positions();
+
+        self.emit(
+            QueueEvent::new(queue_id, call_id, QueueEventType::Overflowed)
+                .with_wait(queued_call.wait_time),
+        );
+
+        Ok(queued_call)
+    }
+
+    /// Take the waiting callers an overflow rule applies to at `now` out of
+    /// the queue, e.g. once they waited too long or the last agent logged
+    /// out
+    ///
+    /// Places held for callbacks are left alone.
+    pub fn take_overflows(
+        &self,
+        queue_id: Uuid,
+        now: DateTime<Utc>,
+    ) -> Result<Vec<(QueuedCall, OverflowRule)>, QueueEngineError> {
+        let mut sessions = self.sessions.lock().unwrap();
+        let session = sessions
+            .get_mut(&queue_id)
+            .ok_or(QueueEngineError::QueueNotFound)?;
+        if session.queue.overflow_rules.is_empty() {
+            return Ok(Vec::new());
+        }
+
+        let agents_logged_in = session.agents_logged_in();
+        let mut overflowed = Vec::new();
+        let mut index = 0;
+        while index < session.waiting_calls.len() {
+            let call = &session.waiting_calls[index];
+            let check = OverflowCheck {
+                now,
+                position: index + 1,
+                wait: (now - call.enqueued_at).to_std().unwrap_or_default(),
+                agents_logged_in,
+            };
+            let rule = match call.callback_id {
+                Some(_) => None,
+                None => session.queue.overflow_rule(&check).cloned(),
+            };
+            match rule {
+                Some(rule) => {
+                    let mut call = session.waiting_calls.remove(index).unwrap();
+                    call.wait_time = check.wait;
+                    overflowed.push((call, rule));
+                }
+                None => index += 1,
+            }
+        }
+        if overflowed.is_empty() {
+            return Ok(overflowed);
+        }
+
+        session.statistics.calls_overflowed += overflowed.len() as u64;
+        session.update_positions();
+        for (call, _) in &overflowed {
+            self.emit(
+                QueueEvent::new(queue_id, call.call_id.clone(), QueueEventType::Overflowed)
+                    .with_occurred_at(now)
+                    .with_wait(call.wait_time),
+            );
+        }
+
+        Ok(overflowed)
+    }
+
+    /// Whether `digit` requests a callback in this queue
+    pub fn is_callback_digit(&self, queue_id: Uuid, digit: char) -> bool {
+        let sessions = self.sessions.lock().unwrap();
+        sessions
+            .get(&queue_id)
+            .is_some_and(|session| session.queue.callback_digit == Some(digit))
+    }
+
+    /// Hold a waiting caller's place in line for a callback to `number`
+    ///
+    /// The caller's call can be released afterwards; the place is dialed
+    /// back once it reaches the head of the queue (see `next_due_callback`).
+    pub fn request_callback(
+        &self,
+        queue_id: Uuid,
+        call_id: &str,
+        number: String,
+    ) -> Result<QueueCallback, QueueEngineError> {
+        let mut sessions = self.sessions.lock().unwrap();
+        let session = sessions
+            .get_mut(&queue_id)
+            .ok_or(QueueEngineError::QueueNotFound)?;
+
+        if session.queue.callback_digit.is_none() {
+            return Err(QueueEngineError::InvalidOperation(
+                "Callbacks are not enabled for this queue".to_string(),
+            ));
+        }
+        let call_index = session
+            .waiting_index(call_id)
+            .ok_or(QueueEngineError::CallNotFound)?;
+
+        let callback = QueueCallback::new(
+            &session.waiting_calls[call_index],
+            number,
+            session.queue.callback_max_attempts,
+        );
+        session.waiting_calls[call_index].callback_id = Some(callback.id);
+        session.callbacks.insert(callback.id, callback.clone());
+
+        Ok(callback)
+    }
+
+    /// Callback to dial now, and the agent reserved for it
+    ///
+    /// A callback is due once it is first in line, not counting callbacks
+    /// being dialed or waiting to retry, and an agent is available. The
+    /// agent stays busy until `callback_connected` or `callback_failed`.
+    pub fn next_due_callback(
+        &self,
+        queue_id: Uuid,
+    ) -> Result<Option<(QueueCallback, QueueMember)>, QueueEngineError> {
+        let mut sessions = self.sessions.lock().unwrap();
+        let session = sessions
+            .get_mut(&queue_id)
+            .ok_or(QueueEngineError::QueueNotFound)?;
+
+        let now = Utc::now();
+        let callback_id = session
+            .waiting_calls
+            .iter()
+            .find(|c| match c.callback_id {
+                Some(id) => session.callbacks.get(&id).is_some_and(|cb| cb.is_due(now)),
+                None => true,
+            })
+            .and_then(|c| c.callback_id);
+        let callback_id = match callback_id {
+            Some(id) => id,
+            None => return Ok(None),
+        };
+        let agent = match session.select_agent().and_then(|id| session.members.get_mut(&id)) {
+            Some(agent) => {
+                agent.mark_busy();
+                agent.clone()
+            }
+            None => return Ok(None),
+        };
+
+        let callback = session
+            .callbacks
+            .get_mut(&callback_id)
+            .ok_or(QueueEngineError::CallbackNotFound)?;
+        callback.status = CallbackStatus::Dialing;
+        callback.attempts += 1;
+        callback.next_attempt_at = None;
+
+        Ok(Some((callback.clone(), agent)))
+    }
+
+    /// The callback call `call_id` was answered and connected to the agent
+    pub fn callback_connected(
+        &self,
+        queue_id: Uuid,
+        callback_id: Uuid,
+        call_id: &str,
+        agent_id: Uuid,
+    ) -> Result<QueueCallback, QueueEngineError> {
+        let mut sessions = self.sessions.lock().unwrap();
+        let session = sessions
+            .get_mut(&queue_id)
+            .ok_or(QueueEngineError::QueueNotFound)?;
+
+        match session.callbacks.get(&callback_id) {
+            Some(callback) if callback.status == CallbackStatus::Dialing => {}
+            Some(_) => {
+                return Err(QueueEngineError::InvalidOperation(
+                    "Callback is not being dialed".to_string(),
+                ))
+            }
+            None => return Err(QueueEngineError::CallbackNotFound),
+        }
+        let mut callback = session.callbacks.remove(&callback_id).unwrap();
+        let queued_call = match session.callback_index(callback_id) {
+            Some(index) => session.connect_waiting(index, call_id, agent_id),
+            None => return Err(QueueEngineError::CallNotFound),
+        };
+        callback.status = CallbackStatus::Connected;
+        callback.attempt_call_ids.push(call_id.to_string());
+
+        // Reported under the original call, which waited in the queue
+        let mut event = QueueEvent::new(queue_id, queued_call.call_id, QueueEventType::Answered)
+            .with_wait(queued_call.wait_time);
+        if let Some(user_id) = session.agent_user_id(agent_id) {
+            event = event.with_agent(user_id);
+        }
+        self.emit(event);
+
+        Ok(callback)
+    }
+
+    /// The callback call `call_id` failed; retried after the queue's
+    /// retry delay until the attempts run out
+    pub fn callback_failed(
+        &self,
+        queue_id: Uuid,
+        callback_id: Uuid,
+        call_id: &str,
+        agent_id: Uuid,
+        reason: &str,
+    ) -> Result<QueueCallback, QueueEngineError> {
+        let mut sessions = self.sessions.lock().unwrap();
+        let session = sessions
+            .get_mut(&queue_id)
+            .ok_or(QueueEngineError::QueueNotFound)?;
+
+        // Release the agent reserved for the attempt
+        if let Some(agent) = session.members.get_mut(&agent_id) {
+            if agent.status == AgentStatus::Busy {
+                agent.mark_available();
+            }
+        }
+
+        let retry_delay = session.queue.callback_retry_delay;
+        let callback = session
+            .callbacks
+            .get_mut(&callback_id)
+            .ok_or(QueueEngineError::CallbackNotFound)?;
+        callback.attempt_call_ids.push(call_id.to_string());
+        callback.last_failure = Some(reason.to_string());
+
+        if callback.attempts < callback.max_attempts {
+            callback.status = CallbackStatus::Pending;
+            callback.next_attempt_at =
+                Some(Utc::now() + chrono::Duration::from_std(retry_delay).unwrap_or_default());
+            return Ok(callback.clone());
+        }
+
+        let mut callback = session.callbacks.remove(&callback_id).unwrap();
+        callback.status = CallbackStatus::Abandoned;
+        if let Some(index) = session.callback_index(callback_id) {
+            let mut queued_call = session.waiting_calls.remove(index).unwrap();
+            queued_call.update_wait_time();
+            session.statistics.calls_abandoned += 1;
+            session.update_positions();
+
+            self.emit(
+                QueueEvent::new(queue_id, queued_call.call_id, QueueEventType::Abandoned)
+                    .with_wait(queued_call.wait_time),
+            );
+        }
+
+        Ok(callback)
+    }
+
+    /// Cancel a callback that is not being dialed, giving up its place
+    pub fn cancel_callback(
+        &self,
+        queue_id: Uuid,
+        callback_id: Uuid,
+    ) -> Result<QueueCallback, QueueEngineError> {
+        let mut sessions = self.sessions.lock().unwrap();
+        let session = sessions
+            .get_mut(&queue_id)
+            .ok_or(QueueEngineError::QueueNotFound)?;
+
+        match session.callbacks.get(&callback_id) {
+            Some(callback) if callback.status == CallbackStatus::Dialing => {
+                return Err(QueueEngineError::InvalidOperation(
+                    "Callback is being dialed".to_string(),
+                ))
+            }
+            Some(_) => {}
+            None => return Err(QueueEngineError::CallbackNotFound),
+        }
+        let mut callback = session.callbacks.remove(&callback_id).unwrap();
+        callback.status = CallbackStatus::Cancelled;
+        if let Some(index) = session.callback_index(callback_id) {
+            session.waiting_calls.remove(index);
+            session.update_positions();
+        }
+
+        Ok(callback)
+    }
+
+    /// Callbacks of a queue in line order
+    pub fn list_callbacks(&self, queue_id: Uuid) -> Result<Vec<QueueCallback>, QueueEngineError> {
+        let sessions = self.sessions.lock().unwrap();
+        let session = sessions
+            .get(&queue_id)
+            .ok_or(QueueEngineError::QueueNotFound)?;
+
+        Ok(session
+            .waiting_calls
+            .iter()
+            .filter_map(|c| c.callback_id.and_then(|id| session.callbacks.get(&id)))
+            .cloned()
+            .collect())
+    }
+
+    /// IDs of all running queues
+    pub fn queue_ids(&self) -> Vec<Uuid> {
+        self.sessions.lock().unwrap().keys().copied().collect()
+    }
+
     /// Get queue statistics
     pub fn get_statistics(&self, queue_id: Uuid) -> Result<QueueStatistics, QueueEngineError> {
         let sessions = self.sessions.lock().unwrap();
@@ -437,6 +1196,14 @@ impl CallQueueEngine {
         Ok(stats)
     }
 
+    /// Wallboard snapshots of all running queues
+    pub fn snapshots(&self) -> Vec<QueueSnapshot> {
+        let sessions = self.sessions.lock().unwrap();
+        let mut snapshots: Vec<QueueSnapshot> = sessions.values().map(QueueSession::snapshot).collect();
+        snapshots.sort_by(|a, b| a.name.cmp(&b.name));
+        snapshots
+    }
+
     /// Get queue position for a call
     pub fn get_position(&self, queue_id: Uuid, call_id: &str) -> Result<usize, QueueEngineError> {
         let sessions = self.sessions.lock().unwrap();
@@ -507,6 +1274,7 @@ impl Default for CallQueueEngine {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::domain::call_forwarding::TimeRange;
 
     fn create_test_queue() -> CallQueue {
         CallQueue {
@@ -527,6 +1295,13 @@ mod tests {
             periodic_announce_frequency: Duration::from_secs(30),
             overflow_queue_id: None,
             overflow_action: OverflowAction::Voicemail,
+            service_level_threshold: Duration::from_secs(20),
+            callback_digit: None,
+            callback_max_attempts: 3,
+            callback_retry_delay: Duration::from_secs(60),
+            max_missed_offers: 3,
+            inactivity_action: InactivityAction::Pause,
+            overflow_rules: Vec::new(),
             created_at: Utc::now(),
             updated_at: Utc::now(),
         }
@@ -574,7 +1349,7 @@ mod tests {
 
         // Try to add one more
         let result = engine.enqueue_call(queue.id, "call-3".to_string(), "caller3".to_string(), None);
-        assert_eq!(result, Err(QueueEngineError::QueueFull));
+        assert!(matches!(result, Err(QueueEngineError::QueueFull)));
     }
 
     #[test]
@@ -647,6 +1422,328 @@ mod tests {
         assert_eq!(stats.calls_active, 0);
     }
 
+    #[test]
+    fn test_emits_queue_events() {
+        let (tx, mut rx) = mpsc::unbounded_channel();
+        let mut engine = CallQueueEngine::new();
+        engine.set_event_sink(tx);
+        let queue = create_test_queue();
+        engine.start_queue(queue.clone());
+
+        let member = QueueMember::new(42, "agent1".to_string(), "1001".to_string());
+        let member_id = member.id;
+        engine.add_member(queue.id, member).unwrap();
+
+        engine.enqueue_call(queue.id, "call-1".to_string(), "caller".to_string(), None).unwrap();
+        engine.offer_call(queue.id, "call-1", member_id).unwrap();
+        engine.connect_call(queue.id, "call-1", member_id).unwrap();
+
+        let snapshot = engine.snapshots().remove(0);
+        assert_eq!(snapshot.calls_waiting, 0);
+        assert_eq!(snapshot.calls_active, 1);
+        assert_eq!(snapshot.agents.busy, 1);
+
+        engine.end_call(queue.id, "call-1", Duration::from_secs(90)).unwrap();
+        engine.enqueue_call(queue.id, "call-2".to_string(), "caller".to_string(), None).unwrap();
+        engine.abandon_call(queue.id, "call-2").unwrap();
+
+        let mut events = Vec::new();
+        while let Ok(event) = rx.try_recv() {
+            events.push(event);
+        }
+        let types: Vec<QueueEventType> = events.iter().map(|e| e.event_type).collect();
+        assert_eq!(
+            types,
+            vec![
+                QueueEventType::Enqueued,
+                QueueEventType::Offered,
+                QueueEventType::Answered,
+                QueueEventType::Completed,
+                QueueEventType::Enqueued,
+                QueueEventType::Abandoned,
+            ]
+        );
+        assert_eq!(events[1].agent_id, Some(42));
+        assert_eq!(events[3].talk_secs, 90);
+        assert_eq!(events[3].wrap_up_secs, 10);
+    }
+
+    fn answering_service() -> OverflowDestination {
+        OverflowDestination::External {
+            number: "+15550199".to_string(),
+            trunk_id: None,
+        }
+    }
+
+    #[test]
+    fn test_overflow_on_arrival_outside_hours() {
+        let (tx, mut rx) = mpsc::unbounded_channel();
+        let mut engine = CallQueueEngine::new();
+        engine.set_event_sink(tx);
+        let mut queue = create_test_queue();
+        queue.overflow_rules = vec![OverflowRule {
+            condition: OverflowCondition::OutsideHours {
+                hours: TimeRange::business_hours(),
+            },
+            destination: answering_service(),
+            announcement: Some("office_closed.wav".to_string()),
+        }];
+        engine.start_queue(queue.clone());
+
+        // Saturday morning: straight to the answering service
+        let saturday = DateTime::parse_from_rfc3339("2025-11-08T10:00:00Z")
+            .unwrap()
+            .with_timezone(&Utc);
+        let arrival = engine
+            .arrive(queue.id, "call-1".to_string(), "caller".to_string(), None, saturday)
+            .unwrap();
+        match arrival {
+            QueueArrival::Overflowed(rule) => {
+                assert_eq!(rule.destination, answering_service());
+                assert_eq!(rule.announcement.as_deref(), Some("office_closed.wav"));
+            }
+            QueueArrival::Queued(_) => panic!("call queued outside business hours"),
+        }
+
+        // Monday morning: queued
+        let monday = saturday + chrono::Duration::days(2);
+        let arrival = engine
+            .arrive(queue.id, "call-2".to_string(), "caller".to_string(), None, monday)
+            .unwrap();
+        assert!(matches!(arrival, QueueArrival::Queued(_)));
+
+        let stats = engine.get_statistics(queue.id).unwrap();
+        assert_eq!(stats.calls_overflowed, 1);
+        assert_eq!(stats.calls_waiting, 1);
+
+        let overflowed = rx.try_recv().unwrap();
+        assert_eq!(overflowed.event_type, QueueEventType::Overflowed);
+        assert_eq!(overflowed.call_id, "call-1");
+        assert_eq!(rx.try_recv().unwrap().event_type, QueueEventType::Enqueued);
+    }
+
+    #[test]
+    fn test_overflow_while_waiting() {
+        let (tx, mut rx) = mpsc::unbounded_channel();
+        let mut engine = CallQueueEngine::new();
+        engine.set_event_sink(tx);
+        let mut queue = create_test_queue();
+        queue.overflow_rules = vec![OverflowRule {
+            condition: OverflowCondition::WaitAbove { secs: 120 },
+            destination: answering_service(),
+            announcement: None,
+        }];
+        engine.start_queue(queue.clone());
+
+        let first = engine
+            .enqueue_call(queue.id, "call-1".to_string(), "caller".to_string(), None)
+            .unwrap();
+        let mut clock = first.enqueued_at + chrono::Duration::seconds(60);
+        engine
+            .arrive(queue.id, "call-2".to_string(), "caller".to_string(), None, clock)
+            .unwrap();
+        assert!(engine.take_overflows(queue.id, clock).unwrap().is_empty());
+
+        // Two minutes in, only the first caller crossed the threshold
+        clock = first.enqueued_at + chrono::Duration::seconds(121);
+        let overflowed = engine.take_overflows(queue.id, clock).unwrap();
+        assert_eq!(overflowed.len(), 1);
+        assert_eq!(overflowed[0].0.call_id, "call-1");
+        assert_eq!(overflowed[0].0.wait_time, Duration::from_secs(121));
+        assert_eq!(overflowed[0].1.destination, answering_service());
+
+        let waiting = engine.get_waiting_calls(queue.id).unwrap();
+        assert_eq!(waiting.len(), 1);
+        assert_eq!(waiting[0].position, 1);
+
+        // The second one follows once it waited as long
+        clock += chrono::Duration::seconds(120);
+        let overflowed = engine.take_overflows(queue.id, clock).unwrap();
+        assert_eq!(overflowed.len(), 1);
+        assert_eq!(overflowed[0].0.call_id, "call-2");
+        assert_eq!(engine.get_statistics(queue.id).unwrap().calls_overflowed, 2);
+
+        let events: Vec<QueueEvent> = std::iter::from_fn(|| rx.try_recv().ok())
+            .filter(|e| e.event_type == QueueEventType::Overflowed)
+            .collect();
+        assert_eq!(events.len(), 2);
+        assert_eq!(events[0].wait_secs, 121);
+    }
+
+    #[test]
+    fn test_callback_keeps_place_in_line() {
+        let engine = CallQueueEngine::new();
+
+        // Disabled unless the queue has a callback digit
+        let disabled = create_test_queue();
+        engine.start_queue(disabled.clone());
+        engine.enqueue_call(disabled.id, "call-0".to_string(), "caller0".to_string(), None).unwrap();
+        assert!(matches!(
+            engine.request_callback(disabled.id, "call-0", "5550100".to_string()),
+            Err(QueueEngineError::InvalidOperation(_))
+        ));
+
+        let mut queue = create_test_queue();
+        queue.callback_digit = Some('5');
+        engine.start_queue(queue.clone());
+        engine.enqueue_call(queue.id, "call-1".to_string(), "sip:5550100@example.com".to_string(), None).unwrap();
+        engine.enqueue_call(queue.id, "call-2".to_string(), "caller2".to_string(), None).unwrap();
+
+        assert!(engine.is_callback_digit(queue.id, '5'));
+        assert!(!engine.is_callback_digit(queue.id, '1'));
+        let callback = engine.request_callback(queue.id, "call-1", "5550100".to_string()).unwrap();
+        assert_eq!(callback.status, CallbackStatus::Pending);
+
+        // The released call hanging up does not give up the place
+        assert_eq!(engine.abandon_call(queue.id, "call-1"), Err(QueueEngineError::CallNotFound));
+        assert_eq!(engine.get_position(queue.id, "call-2").unwrap(), 2);
+
+        // Nothing to dial until an agent is available
+        assert!(engine.next_due_callback(queue.id).unwrap().is_none());
+        let member = QueueMember::new(7, "agent1".to_string(), "1001".to_string());
+        engine.add_member(queue.id, member).unwrap();
+
+        let (due, agent) = engine.next_due_callback(queue.id).unwrap().unwrap();
+        assert_eq!(due.id, callback.id);
+        assert_eq!(due.status, CallbackStatus::Dialing);
+        assert_eq!(due.attempts, 1);
+        assert_eq!(engine.get_available_agents(queue.id).unwrap(), 0);
+
+        let connected = engine.callback_connected(queue.id, due.id, "callback-leg-1", agent.id).unwrap();
+        assert_eq!(connected.status, CallbackStatus::Connected);
+        assert_eq!(connected.attempt_call_ids, vec!["callback-leg-1".to_string()]);
+        assert!(engine.list_callbacks(queue.id).unwrap().is_empty());
+        assert_eq!(engine.get_position(queue.id, "call-2").unwrap(), 1);
+
+        let stats = engine.get_statistics(queue.id).unwrap();
+        assert_eq!(stats.calls_answered, 1);
+        assert_eq!(stats.calls_active, 1);
+        engine.end_call(queue.id, "callback-leg-1", Duration::from_secs(30)).unwrap();
+    }
+
+    #[test]
+    fn test_callback_retries_then_abandons() {
+        let engine = CallQueueEngine::new();
+        let mut queue = create_test_queue();
+        queue.callback_digit = Some('5');
+        queue.callback_max_attempts = 2;
+        queue.callback_retry_delay = Duration::from_secs(0);
+        engine.start_queue(queue.clone());
+        engine.add_member(queue.id, QueueMember::new(7, "agent1".to_string(), "1001".to_string())).unwrap();
+        engine.enqueue_call(queue.id, "call-1".to_string(), "caller".to_string(), None).unwrap();
+        engine.request_callback(queue.id, "call-1", "5550100".to_string()).unwrap();
+
+        let (due, agent) = engine.next_due_callback(queue.id).unwrap().unwrap();
+        let retry = engine.callback_failed(queue.id, due.id, "attempt-1", agent.id, "No answer").unwrap();
+        assert_eq!(retry.status, CallbackStatus::Pending);
+        assert_eq!(retry.last_failure.as_deref(), Some("No answer"));
+        assert_eq!(engine.get_available_agents(queue.id).unwrap(), 1);
+        assert_eq!(engine.list_callbacks(queue.id).unwrap().len(), 1);
+
+        let (due, agent) = engine.next_due_callback(queue.id).unwrap().unwrap();
+        assert_eq!(due.attempts, 2);
+        let abandoned = engine.callback_failed(queue.id, due.id, "attempt-2", agent.id, "Busy").unwrap();
+        assert_eq!(abandoned.status, CallbackStatus::Abandoned);
+        assert_eq!(abandoned.attempt_call_ids.len(), 2);
+
+        let stats = engine.get_statistics(queue.id).unwrap();
+        assert_eq!(stats.calls_abandoned, 1);
+        assert_eq!(stats.calls_waiting, 0);
+        assert!(engine.next_due_callback(queue.id).unwrap().is_none());
+
+        // Cancelling frees the place
+        engine.enqueue_call(queue.id, "call-2".to_string(), "caller".to_string(), None).unwrap();
+        let callback = engine.request_callback(queue.id, "call-2", "5550101".to_string()).unwrap();
+        assert_eq!(engine.cancel_callback(queue.id, callback.id).unwrap().status, CallbackStatus::Cancelled);
+        assert_eq!(engine.cancel_callback(queue.id, callback.id).unwrap_err(), QueueEngineError::CallbackNotFound);
+        assert_eq!(engine.get_statistics(queue.id).unwrap().calls_waiting, 0);
+    }
+
+    #[test]
+    fn test_missed_offers_pause_agent() {
+        let engine = CallQueueEngine::new();
+        let mut states = engine.subscribe_agent_states();
+        let queue = create_test_queue();
+        engine.start_queue(queue.clone());
+        let member = QueueMember::new(42, "agent1".to_string(), "1001".to_string());
+        let member_id = member.id;
+        engine.add_member(queue.id, member).unwrap();
+
+        // Answering resets the count
+        engine.offer_missed(queue.id, member_id).unwrap();
+        engine.offer_missed(queue.id, member_id).unwrap();
+        engine.enqueue_call(queue.id, "call-1".to_string(), "caller".to_string(), None).unwrap();
+        engine.connect_call(queue.id, "call-1", member_id).unwrap();
+        engine.end_call(queue.id, "call-1", Duration::from_secs(30)).unwrap();
+
+        assert_eq!(engine.offer_missed(queue.id, member_id).unwrap(), None);
+        assert_eq!(engine.offer_missed(queue.id, member_id).unwrap(), None);
+        let change = engine.offer_missed(queue.id, member_id).unwrap().unwrap();
+        assert_eq!(change.agent_id, 42);
+        assert_eq!(change.from, AgentAvailability::Available);
+        assert_eq!(change.to, AgentAvailability::Paused);
+        assert_eq!(change.reason, AgentStateReason::MissedOffers);
+        assert_eq!(engine.get_available_agents(queue.id).unwrap(), 0);
+        assert_eq!(states.try_recv().unwrap(), change);
+
+        // Logging out as well, once resumed
+        let mut logout = create_test_queue();
+        logout.max_missed_offers = 1;
+        logout.inactivity_action = InactivityAction::LogOut;
+        engine.start_queue(logout.clone());
+        let member = QueueMember::new(42, "agent1".to_string(), "1001".to_string());
+        let logout_member = member.id;
+        engine.add_member(logout.id, member).unwrap();
+        let change = engine.offer_missed(logout.id, logout_member).unwrap().unwrap();
+        assert_eq!(change.to, AgentAvailability::LoggedOut);
+
+        let resumed = engine
+            .set_agent_availability(queue.id, member_id, AgentAvailability::Available, AgentStateReason::Manual)
+            .unwrap()
+            .unwrap();
+        assert_eq!(resumed.from, AgentAvailability::Paused);
+        assert_eq!(engine.get_available_agents(queue.id).unwrap(), 1);
+
+        let reasons: Vec<AgentStateReason> = engine.agent_state_history(42).iter().map(|c| c.reason).collect();
+        assert_eq!(
+            reasons,
+            vec![AgentStateReason::MissedOffers, AgentStateReason::MissedOffers, AgentStateReason::Manual]
+        );
+    }
+
+    #[test]
+    fn test_dnd_pauses_linked_agent_in_all_queues() {
+        let engine = CallQueueEngine::new();
+        let first = create_test_queue();
+        let second = create_test_queue();
+        engine.start_queue(first.clone());
+        engine.start_queue(second.clone());
+        for queue in [&first, &second] {
+            let mut member = QueueMember::new(5, "bob".to_string(), "1005".to_string());
+            member.dnd_linked = true;
+            engine.add_member(queue.id, member).unwrap();
+        }
+        // Paused by hand before DND: stays paused afterwards
+        let mut manual = QueueMember::new(6, "carol".to_string(), "1006".to_string());
+        manual.dnd_linked = true;
+        manual.pause(Some("Lunch".to_string()));
+        engine.add_member(first.id, manual).unwrap();
+
+        let changes = engine.set_agent_dnd("bob", true);
+        assert_eq!(changes.len(), 2);
+        assert!(changes.iter().all(|c| c.reason == AgentStateReason::DndEnabled));
+        assert_eq!(engine.get_available_agents(first.id).unwrap(), 0);
+        assert_eq!(engine.get_available_agents(second.id).unwrap(), 0);
+
+        assert!(engine.set_agent_dnd("carol", true).is_empty());
+        assert!(engine.set_agent_dnd("carol", false).is_empty());
+
+        let changes = engine.set_agent_dnd("bob", false);
+        assert_eq!(changes.len(), 2);
+        assert!(changes.iter().all(|c| c.to == AgentAvailability::Available));
+        assert_eq!(engine.get_available_agents(first.id).unwrap(), 1);
+    }
+
     #[test]
     fn test_get_next_agent_round_robin() {
         let engine = CallQueueEngine::new();
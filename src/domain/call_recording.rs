@@ -3,6 +3,11 @@
 //! Provides functionality to record calls for compliance, quality monitoring,
 //! and training purposes. Supports both single-party and multi-party recordings.
 
+use crate::domain::privacy::{
+    ErasureSubject, Pseudonymizer, SubjectBearing, SubjectReference, SubjectStore,
+};
+use crate::infrastructure::storage::{StorageCategory, StorageGuard};
+use async_trait::async_trait;
 use chrono::{DateTime, Utc};
 use std::collections::HashMap;
 use std::fs::{self, File};
@@ -311,6 +316,8 @@ pub struct CallRecordingManager {
     default_format: RecordingFormat,
     default_quality: RecordingQuality,
     auto_record: bool,
+    /// Quota and free space checked before each recording
+    storage: Option<Arc<StorageGuard>>,
 }
 
 impl CallRecordingManager {
@@ -322,14 +329,26 @@ impl CallRecordingManager {
             default_format: RecordingFormat::Wav,
             default_quality: RecordingQuality::Standard,
             auto_record: false,
+            storage: None,
         }
     }
 
+    /// Refuse recordings the storage guard does not allow
+    pub fn with_storage_guard(mut self, storage: Arc<StorageGuard>) -> Self {
+        self.storage = Some(storage);
+        self
+    }
+
     /// Enable automatic recording for all calls
     pub fn enable_auto_record(&mut self) {
         self.auto_record = true;
     }
 
+    /// Whether every answered call is recorded
+    pub fn is_auto_record(&self) -> bool {
+        self.auto_record
+    }
+
     /// Set default recording format
     pub fn set_default_format(&mut self, format: RecordingFormat) {
         self.default_format = format;
@@ -355,6 +374,12 @@ impl CallRecordingManager {
             return Err(format!("Call {} is already being recorded", call_id));
         }
 
+        if let Some(storage) = &self.storage {
+            storage
+                .check(StorageCategory::Recordings)
+                .map_err(|e| format!("Recording of call {} refused: {}", call_id, e))?;
+        }
+
         // Generate filename
         let timestamp = Utc::now().format("%Y%m%d_%H%M%S");
         let filename = format!("{}_{}.{}", call_id, timestamp, self.default_format.extension());
@@ -505,6 +530,58 @@ impl CallRecordingManager {
     }
 }
 
+#[async_trait]
+impl SubjectStore for CallRecordingManager {
+    fn name(&self) -> &'static str {
+        "recordings"
+    }
+
+    fn subject_fields(&self) -> &'static [&'static str] {
+        RecordingMetadata::SUBJECT_FIELDS
+    }
+
+    async fn find_subject(&self, subject: &ErasureSubject) -> Result<Vec<SubjectReference>, String> {
+        let completed = self.completed_recordings.lock().unwrap();
+        Ok(completed
+            .iter()
+            .filter_map(|r| {
+                SubjectReference::to(r, r.id, r.started_at, subject)
+                    .map(|reference| reference.with_media(r.filename.clone()))
+            })
+            .collect())
+    }
+
+    async fn pseudonymize(
+        &self,
+        subject: &ErasureSubject,
+        pseudonyms: &Pseudonymizer,
+        records: &[SubjectReference],
+    ) -> Result<u64, String> {
+        let mut completed = self.completed_recordings.lock().unwrap();
+        let mut count = 0;
+        for recording in completed
+            .iter_mut()
+            .filter(|r| records.iter().any(|record| record.record_id == r.id.to_string()))
+        {
+            if recording.pseudonymize(subject, pseudonyms) {
+                count += 1;
+            }
+        }
+        Ok(count)
+    }
+
+    async fn erase_media(&self, records: &[SubjectReference]) -> Result<u64, String> {
+        let mut count = 0;
+        for record in records {
+            if let Ok(id) = record.record_id.parse() {
+                self.delete_recording(id)?;
+                count += 1;
+            }
+        }
+        Ok(count)
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -624,6 +701,36 @@ mod tests {
         assert_eq!(manager.default_quality, RecordingQuality::High);
     }
 
+    #[test]
+    fn test_recording_refused_on_low_disk_space() {
+        use crate::infrastructure::storage::{CategoryConfig, StorageConfig};
+        use crate::test_support::FakeFs;
+
+        let temp_dir = env::temp_dir().join("yakyak_test_low_space");
+        let storage = StorageGuard::with_fs(
+            StorageConfig {
+                recordings: CategoryConfig {
+                    dir: Some(temp_dir.clone()),
+                    ..Default::default()
+                },
+                ..Default::default()
+            },
+            Arc::new(FakeFs::new(10 << 30, 100 << 20)),
+        );
+        let manager = CallRecordingManager::new(temp_dir).with_storage_guard(Arc::new(storage));
+
+        let error = manager
+            .start_recording(
+                "call-full".to_string(),
+                "alice@example.com".to_string(),
+                "bob@example.com".to_string(),
+                RecordingDirection::Both,
+            )
+            .unwrap_err();
+        assert!(error.contains("Not enough disk space for recordings"));
+        assert!(manager.get_active_recordings().is_empty());
+    }
+
     #[test]
     fn test_delete_recording() {
         let temp_dir = env::temp_dir().join("yakyak_test_delete");
@@ -0,0 +1,418 @@
+//! Pre-answer call screening
+//!
+//! Calls to a user with screening enabled are answered by the PBX, which
+//! asks the caller to record their name and then rings the user. When the
+//! user answers, they hear who is calling and choose with a digit: 1 takes
+//! the call, 2 sends it to voicemail (with the recorded name attached) and
+//! 3 rejects it. Callers on the user's exemption list are put through
+//! directly.
+
+use crate::domain::call::EndReason;
+use crate::domain::call_announcer::{AnnouncementRequest, AnnouncementType};
+use crate::domain::routing::simulation::{Explain, Explanation, RoutedCall};
+use crate::domain::shared::NumberingPlan;
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+
+/// Screening settings of one user
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ScreeningSettings {
+    pub enabled: bool,
+    /// Callers put through unscreened: usernames, numbers or full URIs
+    pub exempt: Vec<String>,
+}
+
+impl ScreeningSettings {
+    /// `caller_uri` is on the exemption list
+    pub fn is_exempt(&self, caller_uri: &str) -> bool {
+        let address = address_of(caller_uri);
+        let user = address.split('@').next().unwrap_or_default();
+        self.exempt.iter().any(|entry| {
+            let entry = address_of(entry);
+            entry.eq_ignore_ascii_case(&address) || (!entry.contains('@') && entry == user)
+        })
+    }
+
+    /// `caller_uri` is on the exemption list, numbers matching in any
+    /// presentation (`+14155551234` is exempted by `(415) 555-1234`)
+    pub fn is_exempt_number(
+        &self,
+        caller_uri: &str,
+        plan: &NumberingPlan,
+        tenant: Option<&str>,
+    ) -> bool {
+        if self.is_exempt(caller_uri) {
+            return true;
+        }
+        let address = address_of(caller_uri);
+        let user = address.split('@').next().unwrap_or_default();
+        self.exempt.iter().any(|entry| {
+            let entry = address_of(entry);
+            let entry_user = entry.split('@').next().unwrap_or_default();
+            plan.same_number(entry_user, user, tenant)
+        })
+    }
+}
+
+/// Screening prompts and per-user settings
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ScreeningPolicy {
+    /// Voicemail of a screened user; `{user}` is replaced by their username
+    pub voicemail_uri: String,
+    /// Seconds the caller gets to record their name
+    pub name_max_secs: u64,
+    /// Seconds the user gets to choose before the call goes to voicemail
+    pub choice_timeout_secs: u64,
+    /// Asks the caller to say their name
+    pub record_name_prompt: String,
+    /// Played to the user before the caller's name
+    pub call_from_prompt: String,
+    /// "Press 1 to accept, 2 to send to voicemail, 3 to reject"
+    pub options_prompt: String,
+    /// Played to a rejected caller
+    pub declined_prompt: String,
+    /// Per-user settings, keyed by `user@realm`
+    pub users: HashMap<String, ScreeningSettings>,
+}
+
+impl Default for ScreeningPolicy {
+    fn default() -> Self {
+        Self {
+            voicemail_uri: "sip:vm-{user}@localhost".to_string(),
+            name_max_secs: 5,
+            choice_timeout_secs: 15,
+            record_name_prompt: "screening_record_name".to_string(),
+            call_from_prompt: "screening_call_from".to_string(),
+            options_prompt: "screening_options".to_string(),
+            declined_prompt: "screening_declined".to_string(),
+            users: HashMap::new(),
+        }
+    }
+}
+
+impl ScreeningPolicy {
+    pub fn choice_timeout(&self) -> Duration {
+        Duration::from_secs(self.choice_timeout_secs)
+    }
+
+    /// Voicemail of the user at `callee_uri`
+    pub fn voicemail_target(&self, callee_uri: &str) -> String {
+        let address = address_of(callee_uri);
+        let user = address.split('@').next().unwrap_or_default();
+        self.voicemail_uri.replace("{user}", user)
+    }
+}
+
+/// What the screened user chose
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ScreeningChoice {
+    Accept,
+    Voicemail,
+    Reject,
+}
+
+impl ScreeningChoice {
+    pub fn from_digit(digit: char) -> Option<Self> {
+        match digit {
+            '1' => Some(ScreeningChoice::Accept),
+            '2' => Some(ScreeningChoice::Voicemail),
+            '3' => Some(ScreeningChoice::Reject),
+            _ => None,
+        }
+    }
+}
+
+/// Step of a screened call
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ScreeningState {
+    /// The caller is recording their name
+    RecordingName,
+    /// The user is being rung; the caller hears ringback
+    RingingCallee,
+    /// The user answered and hears the name and options
+    AwaitingChoice,
+    Decided(ScreeningChoice),
+}
+
+/// One screened call, from the name recording to the user's choice
+#[derive(Debug, Clone)]
+pub struct ScreeningFlow {
+    pub call_id: String,
+    pub caller_uri: String,
+    pub callee_uri: String,
+    state: ScreeningState,
+    /// Recording of the caller's name
+    name_recording: Option<String>,
+}
+
+impl ScreeningFlow {
+    pub fn new(call_id: String, caller_uri: String, callee_uri: String) -> Self {
+        Self {
+            call_id,
+            caller_uri,
+            callee_uri,
+            state: ScreeningState::RecordingName,
+            name_recording: None,
+        }
+    }
+
+    pub fn state(&self) -> ScreeningState {
+        self.state
+    }
+
+    pub fn name_recording(&self) -> Option<&str> {
+        self.name_recording.as_deref()
+    }
+
+    /// Prompt asking the caller for their name
+    pub fn record_name_prompt(&self, policy: &ScreeningPolicy) -> AnnouncementRequest {
+        AnnouncementRequest::new(self.call_id.clone(), AnnouncementType::Custom)
+            .add_audio(&policy.record_name_prompt)
+            .to_party(&self.caller_uri)
+    }
+
+    /// The caller's name is recorded; the user is rung next
+    pub fn name_recorded(&mut self, recording: &str) -> Result<(), String> {
+        if self.state != ScreeningState::RecordingName {
+            return Err(format!("Call {} is not recording a name", self.call_id));
+        }
+        self.name_recording = Some(recording.to_string());
+        self.state = ScreeningState::RingingCallee;
+        Ok(())
+    }
+
+    /// The user answered; returns what they hear: "call from", the
+    /// caller's name and the options
+    pub fn callee_answered(&mut self, policy: &ScreeningPolicy) -> Result<AnnouncementRequest, String> {
+        let recording = match (&self.state, &self.name_recording) {
+            (ScreeningState::RingingCallee, Some(recording)) => recording.clone(),
+            _ => return Err(format!("Call {} is not ringing a screened user", self.call_id)),
+        };
+        self.state = ScreeningState::AwaitingChoice;
+        Ok(
+            AnnouncementRequest::new(self.call_id.clone(), AnnouncementType::Custom)
+                .add_audio(&policy.call_from_prompt)
+                .add_audio(&recording)
+                .add_audio(&policy.options_prompt)
+                .to_party(&self.callee_uri),
+        )
+    }
+
+    /// Apply a digit pressed by the user; other digits are ignored
+    pub fn choose(&mut self, digit: char) -> Option<ScreeningChoice> {
+        if self.state != ScreeningState::AwaitingChoice {
+            return None;
+        }
+        let choice = ScreeningChoice::from_digit(digit)?;
+        self.state = ScreeningState::Decided(choice);
+        Some(choice)
+    }
+
+    /// The user did not choose in time: the call goes to voicemail
+    pub fn choice_timed_out(&mut self) -> Option<ScreeningChoice> {
+        if self.state != ScreeningState::AwaitingChoice {
+            return None;
+        }
+        self.state = ScreeningState::Decided(ScreeningChoice::Voicemail);
+        Some(ScreeningChoice::Voicemail)
+    }
+
+    /// Prompt played to a rejected caller
+    pub fn declined_prompt(&self, policy: &ScreeningPolicy) -> AnnouncementRequest {
+        AnnouncementRequest::new(self.call_id.clone(), AnnouncementType::Goodbye)
+            .add_audio(&policy.declined_prompt)
+            .to_party(&self.caller_uri)
+    }
+
+    /// How the call is recorded when it ends after this flow: a call sent
+    /// to voicemail was not answered by the user
+    pub fn end_reason(&self) -> Option<EndReason> {
+        match self.state {
+            ScreeningState::Decided(ScreeningChoice::Voicemail) => Some(EndReason::Voicemail),
+            _ => None,
+        }
+    }
+}
+
+/// Screening settings of every user
+pub struct ScreeningService {
+    policy: ScreeningPolicy,
+    users: RwLock<HashMap<String, ScreeningSettings>>,
+    /// Matches exempted numbers by number rather than by string
+    numbering: Option<Arc<NumberingPlan>>,
+}
+
+impl ScreeningService {
+    pub fn new(policy: ScreeningPolicy) -> Self {
+        let users = RwLock::new(policy.users.clone());
+        Self {
+            policy,
+            users,
+            numbering: None,
+        }
+    }
+
+    /// Compare callers with exemption lists as phone numbers, using the
+    /// home country of the callee's realm
+    pub fn with_numbering_plan(mut self, numbering: Arc<NumberingPlan>) -> Self {
+        self.numbering = Some(numbering);
+        self
+    }
+
+    pub fn policy(&self) -> &ScreeningPolicy {
+        &self.policy
+    }
+
+    /// Set the settings of the user at `user_uri`
+    pub fn set_settings(&self, user_uri: &str, settings: ScreeningSettings) {
+        self.users
+            .write()
+            .unwrap()
+            .insert(address_of(user_uri), settings);
+    }
+
+    pub fn settings(&self, user_uri: &str) -> Option<ScreeningSettings> {
+        self.users.read().unwrap().get(&address_of(user_uri)).cloned()
+    }
+
+    /// Calls from `caller_uri` to `callee_uri` are screened
+    pub fn should_screen(&self, caller_uri: &str, callee_uri: &str) -> bool {
+        self.settings(callee_uri).is_some_and(|settings| {
+            let exempt = match &self.numbering {
+                Some(plan) => {
+                    let callee = address_of(callee_uri);
+                    let realm = callee.split_once('@').map(|(_, realm)| realm);
+                    settings.is_exempt_number(caller_uri, plan, realm)
+                }
+                None => settings.is_exempt(caller_uri),
+            };
+            settings.enabled && !exempt
+        })
+    }
+
+    /// Start screening a call, if its callee screens calls from its caller
+    pub fn start(&self, call_id: &str, caller_uri: &str, callee_uri: &str) -> Option<ScreeningFlow> {
+        self.should_screen(caller_uri, callee_uri).then(|| {
+            ScreeningFlow::new(
+                call_id.to_string(),
+                caller_uri.to_string(),
+                callee_uri.to_string(),
+            )
+        })
+    }
+}
+
+/// `user@host` of a SIP URI or name-addr, without scheme or parameters
+#[async_trait]
+impl Explain for ScreeningService {
+    fn step(&self) -> &'static str {
+        "screening"
+    }
+
+    async fn explain(&self, call: &RoutedCall) -> Option<Explanation> {
+        self.should_screen(&call.caller_uri, &call.target_uri).then(|| {
+            Explanation::matched(format!(
+                "{} screens calls from {}: the caller records their name before the callee accepts",
+                address_of(&call.target_uri),
+                call.caller()
+            ))
+        })
+    }
+}
+
+fn address_of(uri: &str) -> String {
+    let uri = match (uri.find('<'), uri.find('>')) {
+        (Some(start), Some(end)) if start < end => &uri[start + 1..end],
+        _ => uri,
+    };
+    uri.trim()
+        .trim_start_matches("sips:")
+        .trim_start_matches("sip:")
+        .trim_start_matches("tel:")
+        .split([';', '?'])
+        .next()
+        .unwrap_or_default()
+        .to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn service() -> ScreeningService {
+        let service = ScreeningService::new(ScreeningPolicy::default());
+        service.set_settings(
+            "sip:ceo@example.com",
+            ScreeningSettings {
+                enabled: true,
+                exempt: vec!["assistant".to_string(), "sip:+15551234@trunk.example.net".to_string()],
+            },
+        );
+        service
+    }
+
+    #[test]
+    fn test_exempt_callers_bypass_screening() {
+        let service = service();
+
+        assert!(service.should_screen("sip:+15559999@trunk.example.net", "sip:ceo@example.com"));
+        assert!(!service.should_screen("sip:assistant@example.com", "sip:ceo@example.com"));
+        assert!(!service.should_screen(
+            "\"Board\" <sip:+15551234@trunk.example.net>;tag=1",
+            "<sip:ceo@example.com>"
+        ));
+        // Users without screening are never screened
+        assert!(!service.should_screen("sip:+15559999@trunk.example.net", "sip:bob@example.com"));
+    }
+
+    #[test]
+    fn test_exempt_numbers_in_any_format() {
+        let service = ScreeningService::new(ScreeningPolicy::default())
+            .with_numbering_plan(Arc::new(NumberingPlan::default()));
+        service.set_settings(
+            "sip:ceo@example.com",
+            ScreeningSettings {
+                enabled: true,
+                exempt: vec!["(415) 555-1234".to_string(), "assistant".to_string()],
+            },
+        );
+
+        assert!(!service.should_screen("sip:+14155551234@trunk.example.net", "sip:ceo@example.com"));
+        assert!(!service.should_screen("sip:14155551234@trunk.example.net", "sip:ceo@example.com"));
+        assert!(!service.should_screen("sip:assistant@example.com", "sip:ceo@example.com"));
+        assert!(service.should_screen("sip:+14155551235@trunk.example.net", "sip:ceo@example.com"));
+    }
+
+    #[test]
+    fn test_flow_steps() {
+        let policy = ScreeningPolicy::default();
+        let mut flow = service()
+            .start("call-1", "sip:+15559999@trunk.example.net", "sip:ceo@example.com")
+            .unwrap();
+        assert_eq!(flow.state(), ScreeningState::RecordingName);
+        assert!(flow.callee_answered(&policy).is_err());
+
+        flow.name_recorded("screening/call-1.wav").unwrap();
+        let prompt = flow.callee_answered(&policy).unwrap();
+        assert_eq!(
+            prompt.audio_files,
+            vec!["screening_call_from", "screening/call-1.wav", "screening_options"]
+        );
+        assert_eq!(prompt.party.as_deref(), Some("sip:ceo@example.com"));
+
+        assert_eq!(flow.choose('9'), None);
+        assert_eq!(flow.choose('2'), Some(ScreeningChoice::Voicemail));
+        assert_eq!(flow.choose('1'), None);
+        assert_eq!(flow.end_reason(), Some(EndReason::Voicemail));
+        assert_eq!(
+            policy.voicemail_target(&flow.callee_uri),
+            "sip:vm-ceo@localhost"
+        );
+    }
+}
@@ -0,0 +1,727 @@
+//! Post-call surveys
+//!
+//! When an agent of a surveyed queue hangs up, the caller stays connected
+//! and is asked a few questions ("rate your experience from 1 to 5"),
+//! answered with a digit each. A question left unanswered is asked again
+//! until its attempts run out. Answers are stored with the call's CDR,
+//! queue and agent; a caller who hangs up or stops answering leaves a
+//! partial response. Each queue surveys only a sample of its callers.
+
+use crate::domain::call_announcer::{AnnouncementRequest, AnnouncementType};
+use crate::domain::routing::lint::{EntityKind, Lint, RoutingInventory};
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::sync::RwLock;
+use std::time::Duration;
+use uuid::Uuid;
+
+/// Jump taken after some answers to a question
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SkipRule {
+    /// Answers the rule applies to
+    pub answers: Vec<u8>,
+    /// Index of the next question; the survey ends when unset
+    #[serde(default)]
+    pub goto: Option<usize>,
+}
+
+/// One question of a survey
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SurveyQuestion {
+    /// Prompt asking the question
+    pub prompt: String,
+    /// Lowest valid answer
+    #[serde(default = "default_min_digit")]
+    pub min_digit: u8,
+    /// Highest valid answer
+    #[serde(default = "default_max_digit")]
+    pub max_digit: u8,
+    /// First matching rule decides the next question, otherwise the
+    /// following one is asked
+    #[serde(default)]
+    pub skip: Vec<SkipRule>,
+}
+
+fn default_min_digit() -> u8 {
+    1
+}
+
+fn default_max_digit() -> u8 {
+    5
+}
+
+impl SurveyQuestion {
+    pub fn new(prompt: impl Into<String>, min_digit: u8, max_digit: u8) -> Self {
+        Self {
+            prompt: prompt.into(),
+            min_digit,
+            max_digit,
+            skip: Vec::new(),
+        }
+    }
+
+    pub fn with_skip(mut self, answers: Vec<u8>, goto: Option<usize>) -> Self {
+        self.skip.push(SkipRule { answers, goto });
+        self
+    }
+
+    /// `digit` answers this question
+    pub fn accepts(&self, digit: char) -> Option<u8> {
+        let value = digit.to_digit(10)? as u8;
+        (self.min_digit..=self.max_digit).contains(&value).then_some(value)
+    }
+}
+
+/// A survey played to callers after their call
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SurveyDefinition {
+    pub name: String,
+    pub questions: Vec<SurveyQuestion>,
+    /// Seconds the caller gets to answer a question
+    #[serde(default = "default_answer_timeout_secs")]
+    pub answer_timeout_secs: u64,
+    /// Times a question is asked before the survey gives up
+    #[serde(default = "default_max_attempts")]
+    pub max_attempts: u32,
+    /// Played before a question is asked again
+    #[serde(default = "default_invalid_prompt")]
+    pub invalid_prompt: String,
+    /// Played once the last question is answered
+    #[serde(default = "default_thanks_prompt")]
+    pub thanks_prompt: String,
+}
+
+fn default_answer_timeout_secs() -> u64 {
+    5
+}
+
+fn default_max_attempts() -> u32 {
+    2
+}
+
+fn default_invalid_prompt() -> String {
+    "survey_invalid".to_string()
+}
+
+fn default_thanks_prompt() -> String {
+    "survey_thanks".to_string()
+}
+
+impl SurveyDefinition {
+    pub fn new(name: impl Into<String>, questions: Vec<SurveyQuestion>) -> Self {
+        Self {
+            name: name.into(),
+            questions,
+            answer_timeout_secs: default_answer_timeout_secs(),
+            max_attempts: default_max_attempts(),
+            invalid_prompt: default_invalid_prompt(),
+            thanks_prompt: default_thanks_prompt(),
+        }
+    }
+
+    pub fn answer_timeout(&self) -> Duration {
+        Duration::from_secs(self.answer_timeout_secs)
+    }
+
+    pub fn validate(&self) -> Result<(), String> {
+        if self.name.is_empty() {
+            return Err("survey name must not be empty".to_string());
+        }
+        if self.questions.is_empty() {
+            return Err(format!("survey {} has no questions", self.name));
+        }
+        if self.max_attempts == 0 {
+            return Err(format!("survey {}: max_attempts must be at least 1", self.name));
+        }
+        for (index, question) in self.questions.iter().enumerate() {
+            if question.prompt.is_empty() {
+                return Err(format!("survey {} question {} has no prompt", self.name, index));
+            }
+            if question.min_digit > question.max_digit || question.max_digit > 9 {
+                return Err(format!(
+                    "survey {} question {}: invalid digit range {}-{}",
+                    self.name, index, question.min_digit, question.max_digit
+                ));
+            }
+            for rule in &question.skip {
+                // Only forward jumps, so every survey ends
+                if rule.goto.is_some_and(|goto| goto <= index || goto >= self.questions.len()) {
+                    return Err(format!(
+                        "survey {} question {}: skip must go to a later question",
+                        self.name, index
+                    ));
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// A queue's survey and the share of its callers asked to take it
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SurveyAssignment {
+    pub queue_id: Uuid,
+    pub survey: String,
+    /// Percentage of callers surveyed
+    #[serde(default = "default_sample_percent")]
+    pub sample_percent: u8,
+}
+
+fn default_sample_percent() -> u8 {
+    100
+}
+
+/// Surveys and the queues they are assigned to
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SurveyPolicy {
+    pub surveys: Vec<SurveyDefinition>,
+    pub assignments: Vec<SurveyAssignment>,
+}
+
+impl SurveyPolicy {
+    pub fn validate(&self) -> Result<(), String> {
+        for survey in &self.surveys {
+            survey.validate()?;
+        }
+        for assignment in &self.assignments {
+            if assignment.sample_percent > 100 {
+                return Err(format!(
+                    "queue {}: sample_percent must be at most 100",
+                    assignment.queue_id
+                ));
+            }
+            if !self.surveys.iter().any(|s| s.name == assignment.survey) {
+                return Err(format!(
+                    "queue {}: unknown survey {}",
+                    assignment.queue_id, assignment.survey
+                ));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// One answered question
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SurveyAnswer {
+    pub question: usize,
+    pub digit: u8,
+}
+
+/// How far a caller got through a survey
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SurveyStatus {
+    Completed,
+    /// The caller hung up or stopped answering
+    Partial,
+}
+
+/// A caller's answers to a survey
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SurveyResponse {
+    pub id: Uuid,
+    pub survey: String,
+    pub call_id: String,
+    pub cdr_id: Option<Uuid>,
+    pub queue_id: Uuid,
+    /// Agent who took the call
+    pub agent: Option<String>,
+    pub answers: Vec<SurveyAnswer>,
+    pub status: SurveyStatus,
+    pub started_at: DateTime<Utc>,
+    pub finished_at: DateTime<Utc>,
+}
+
+/// Survey offered to a call, to be taken when its agent hangs up
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PendingSurvey {
+    pub survey: String,
+    pub queue_id: Uuid,
+    pub agent: Option<String>,
+}
+
+/// One caller taking a survey, question by question
+#[derive(Debug, Clone)]
+pub struct SurveyRun {
+    definition: SurveyDefinition,
+    pending: PendingSurvey,
+    call_id: String,
+    caller_uri: String,
+    cdr_id: Option<Uuid>,
+    /// Question being asked; `None` once the survey is over
+    current: Option<usize>,
+    attempts: u32,
+    answers: Vec<SurveyAnswer>,
+    completed: bool,
+    started_at: DateTime<Utc>,
+}
+
+impl SurveyRun {
+    pub fn new(definition: SurveyDefinition, pending: PendingSurvey, call_id: String, caller_uri: String) -> Self {
+        Self {
+            definition,
+            pending,
+            call_id,
+            caller_uri,
+            cdr_id: None,
+            current: Some(0),
+            attempts: 0,
+            answers: Vec::new(),
+            completed: false,
+            started_at: Utc::now(),
+        }
+    }
+
+    pub fn with_cdr_id(mut self, cdr_id: Uuid) -> Self {
+        self.cdr_id = Some(cdr_id);
+        self
+    }
+
+    pub fn call_id(&self) -> &str {
+        &self.call_id
+    }
+
+    pub fn definition(&self) -> &SurveyDefinition {
+        &self.definition
+    }
+
+    pub fn current(&self) -> Option<usize> {
+        self.current
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.current.is_none()
+    }
+
+    /// Prompt asking the current question, preceded by the invalid-answer
+    /// prompt when it is asked again
+    pub fn prompt(&mut self) -> Option<AnnouncementRequest> {
+        let question = &self.definition.questions[self.current?];
+        let mut request = AnnouncementRequest::new(self.call_id.clone(), AnnouncementType::Custom);
+        if self.attempts > 0 {
+            request = request.add_audio(&self.definition.invalid_prompt);
+        }
+        self.attempts += 1;
+        Some(request.add_audio(&question.prompt).to_party(&self.caller_uri))
+    }
+
+    /// Apply a digit pressed by the caller; returns whether it answered the
+    /// current question
+    pub fn press(&mut self, digit: char) -> bool {
+        let Some(index) = self.current else {
+            return false;
+        };
+        let question = &self.definition.questions[index];
+        let Some(value) = question.accepts(digit) else {
+            return false;
+        };
+        self.answers.push(SurveyAnswer {
+            question: index,
+            digit: value,
+        });
+        let next = match question.skip.iter().find(|rule| rule.answers.contains(&value)) {
+            Some(rule) => rule.goto,
+            None => Some(index + 1).filter(|next| *next < self.definition.questions.len()),
+        };
+        self.current = next;
+        self.attempts = 0;
+        if next.is_none() {
+            self.completed = true;
+        }
+        true
+    }
+
+    /// The current question went unanswered or was answered wrongly;
+    /// returns whether it is asked again
+    pub fn retry(&mut self) -> bool {
+        if self.current.is_none() {
+            return false;
+        }
+        if self.attempts >= self.definition.max_attempts {
+            self.current = None;
+            return false;
+        }
+        true
+    }
+
+    /// The caller left mid-survey
+    pub fn abandon(&mut self) {
+        self.current = None;
+    }
+
+    /// Prompt thanking a caller who answered every question
+    pub fn thanks_prompt(&self) -> Option<AnnouncementRequest> {
+        self.completed.then(|| {
+            AnnouncementRequest::new(self.call_id.clone(), AnnouncementType::Goodbye)
+                .add_audio(&self.definition.thanks_prompt)
+                .to_party(&self.caller_uri)
+        })
+    }
+
+    /// The answers given so far
+    pub fn response(&self) -> SurveyResponse {
+        SurveyResponse {
+            id: Uuid::new_v4(),
+            survey: self.definition.name.clone(),
+            call_id: self.call_id.clone(),
+            cdr_id: self.cdr_id,
+            queue_id: self.pending.queue_id,
+            agent: self.pending.agent.clone(),
+            answers: self.answers.clone(),
+            status: if self.completed {
+                SurveyStatus::Completed
+            } else {
+                SurveyStatus::Partial
+            },
+            started_at: self.started_at,
+            finished_at: Utc::now(),
+        }
+    }
+}
+
+/// Answers to one question of a queue's survey
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct QuestionResults {
+    pub survey: String,
+    pub question: usize,
+    pub answers: u64,
+    /// Number of callers who pressed each digit
+    pub distribution: BTreeMap<u8, u64>,
+    pub average: Option<f64>,
+}
+
+/// Survey scores of one agent
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct AgentSurveyResults {
+    pub agent: String,
+    pub responses: u64,
+    /// Mean of every answer the agent's callers gave
+    pub average: Option<f64>,
+}
+
+/// Survey results of a queue
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct SurveyResults {
+    pub queue_id: Uuid,
+    pub responses: u64,
+    pub completed: u64,
+    pub partial: u64,
+    pub questions: Vec<QuestionResults>,
+    pub agents: Vec<AgentSurveyResults>,
+}
+
+impl SurveyResults {
+    pub fn from_responses(queue_id: Uuid, responses: &[SurveyResponse]) -> Self {
+        let mut questions: BTreeMap<(String, usize), BTreeMap<u8, u64>> = BTreeMap::new();
+        let mut agents: BTreeMap<String, (u64, u64, u64)> = BTreeMap::new();
+        let mut completed = 0;
+        for response in responses {
+            if response.status == SurveyStatus::Completed {
+                completed += 1;
+            }
+            for answer in &response.answers {
+                *questions
+                    .entry((response.survey.clone(), answer.question))
+                    .or_default()
+                    .entry(answer.digit)
+                    .or_default() += 1;
+            }
+            if let Some(agent) = &response.agent {
+                let (count, sum, answers) = agents.entry(agent.clone()).or_default();
+                *count += 1;
+                *sum += response.answers.iter().map(|a| a.digit as u64).sum::<u64>();
+                *answers += response.answers.len() as u64;
+            }
+        }
+
+        let mean = |sum: u64, count: u64| (count > 0).then(|| sum as f64 / count as f64);
+        Self {
+            queue_id,
+            responses: responses.len() as u64,
+            completed,
+            partial: responses.len() as u64 - completed,
+            questions: questions
+                .into_iter()
+                .map(|((survey, question), distribution)| {
+                    let answers = distribution.values().sum();
+                    let sum = distribution.iter().map(|(digit, n)| *digit as u64 * n).sum();
+                    QuestionResults {
+                        survey,
+                        question,
+                        answers,
+                        distribution,
+                        average: mean(sum, answers),
+                    }
+                })
+                .collect(),
+            agents: agents
+                .into_iter()
+                .map(|(agent, (responses, sum, answers))| AgentSurveyResults {
+                    agent,
+                    responses,
+                    average: mean(sum, answers),
+                })
+                .collect(),
+        }
+    }
+}
+
+/// Surveys, their queue assignments, offered calls and stored responses
+pub struct SurveyService {
+    surveys: HashMap<String, SurveyDefinition>,
+    assignments: RwLock<HashMap<Uuid, SurveyAssignment>>,
+    pending: RwLock<HashMap<String, PendingSurvey>>,
+    responses: RwLock<Vec<SurveyResponse>>,
+}
+
+impl SurveyService {
+    pub fn new(policy: SurveyPolicy) -> Self {
+        Self {
+            surveys: policy
+                .surveys
+                .into_iter()
+                .map(|survey| (survey.name.clone(), survey))
+                .collect(),
+            assignments: RwLock::new(
+                policy
+                    .assignments
+                    .into_iter()
+                    .map(|assignment| (assignment.queue_id, assignment))
+                    .collect(),
+            ),
+            pending: RwLock::new(HashMap::new()),
+            responses: RwLock::new(Vec::new()),
+        }
+    }
+
+    pub fn survey(&self, name: &str) -> Option<&SurveyDefinition> {
+        self.surveys.get(name)
+    }
+
+    pub fn assignment(&self, queue_id: Uuid) -> Option<SurveyAssignment> {
+        self.assignments.read().unwrap().get(&queue_id).cloned()
+    }
+
+    /// Survey a sample of a queue's callers
+    pub fn assign(&self, assignment: SurveyAssignment) -> Result<(), String> {
+        if !self.surveys.contains_key(&assignment.survey) {
+            return Err(format!("Unknown survey {}", assignment.survey));
+        }
+        if assignment.sample_percent > 100 {
+            return Err("sample_percent must be at most 100".to_string());
+        }
+        self.assignments
+            .write()
+            .unwrap()
+            .insert(assignment.queue_id, assignment);
+        Ok(())
+    }
+
+    /// A call of `queue_id` was answered by `agent`; returns whether it was
+    /// sampled for the queue's survey
+    pub fn offer(&self, call_id: &str, queue_id: Uuid, agent: Option<String>) -> bool {
+        let Some(assignment) = self.assignment(queue_id) else {
+            return false;
+        };
+        if rand::thread_rng().gen_range(0..100) >= assignment.sample_percent {
+            return false;
+        }
+        self.pending.write().unwrap().insert(
+            call_id.to_string(),
+            PendingSurvey {
+                survey: assignment.survey,
+                queue_id,
+                agent,
+            },
+        );
+        true
+    }
+
+    pub fn is_pending(&self, call_id: &str) -> bool {
+        self.pending.read().unwrap().contains_key(call_id)
+    }
+
+    /// Start the survey offered to a call whose agent hung up
+    pub fn start(&self, call_id: &str, caller_uri: &str) -> Option<SurveyRun> {
+        let pending = self.pending.write().unwrap().remove(call_id)?;
+        let definition = self.surveys.get(&pending.survey)?.clone();
+        Some(SurveyRun::new(definition, pending, call_id.to_string(), caller_uri.to_string()))
+    }
+
+    /// Drop the survey offered to a call the caller ended first
+    pub fn discard(&self, call_id: &str) -> bool {
+        self.pending.write().unwrap().remove(call_id).is_some()
+    }
+
+    pub fn record(&self, response: SurveyResponse) {
+        self.responses.write().unwrap().push(response);
+    }
+
+    pub fn responses(&self, queue_id: Uuid) -> Vec<SurveyResponse> {
+        self.responses
+            .read()
+            .unwrap()
+            .iter()
+            .filter(|response| response.queue_id == queue_id)
+            .cloned()
+            .collect()
+    }
+
+    pub fn results(&self, queue_id: Uuid) -> SurveyResults {
+        SurveyResults::from_responses(queue_id, &self.responses(queue_id))
+    }
+}
+
+#[async_trait]
+impl Lint for SurveyService {
+    fn source(&self) -> &'static str {
+        "surveys"
+    }
+
+    /// Queues surveys are assigned to
+    async fn inventory(&self, inventory: &mut RoutingInventory) {
+        let assignments: Vec<SurveyAssignment> =
+            self.assignments.read().unwrap().values().cloned().collect();
+        for assignment in assignments {
+            inventory.reference(
+                &format!("survey:{}", assignment.survey),
+                EntityKind::Queue,
+                None,
+                &assignment.queue_id.to_string(),
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn csat() -> SurveyDefinition {
+        SurveyDefinition::new(
+            "csat",
+            vec![
+                // A caller who rates 1 is not asked whether the issue was solved
+                SurveyQuestion::new("survey_rate_agent", 1, 5).with_skip(vec![1], None),
+                SurveyQuestion::new("survey_issue_solved", 1, 2),
+            ],
+        )
+    }
+
+    fn service(sample_percent: u8) -> (SurveyService, Uuid) {
+        let queue_id = Uuid::new_v4();
+        let service = SurveyService::new(SurveyPolicy {
+            surveys: vec![csat()],
+            assignments: vec![SurveyAssignment {
+                queue_id,
+                survey: "csat".to_string(),
+                sample_percent,
+            }],
+        });
+        (service, queue_id)
+    }
+
+    #[test]
+    fn test_policy_validation() {
+        let (service, queue_id) = service(100);
+        assert!(service.survey("csat").unwrap().validate().is_ok());
+
+        let mut backwards = csat();
+        backwards.questions[1].skip.push(SkipRule {
+            answers: vec![1],
+            goto: Some(0),
+        });
+        assert!(backwards.validate().is_err());
+
+        let policy = SurveyPolicy {
+            surveys: vec![csat()],
+            assignments: vec![SurveyAssignment {
+                queue_id,
+                survey: "nps".to_string(),
+                sample_percent: 50,
+            }],
+        };
+        assert!(policy.validate().is_err());
+    }
+
+    #[test]
+    fn test_sampling() {
+        let (surveys, queue_id) = service(0);
+        assert!(!surveys.offer("call-1", queue_id, None));
+        assert!(!surveys.offer("call-1", Uuid::new_v4(), None));
+
+        let (surveys, queue_id) = service(100);
+        assert!(surveys.offer("call-1", queue_id, Some("alice".to_string())));
+        assert!(surveys.is_pending("call-1"));
+        assert!(surveys.discard("call-1"));
+        assert!(surveys.start("call-1", "sip:caller@example.com").is_none());
+    }
+
+    #[test]
+    fn test_skip_logic_and_retries() {
+        let (service, queue_id) = service(100);
+        service.offer("call-1", queue_id, None);
+        let mut run = service.start("call-1", "sip:caller@example.com").unwrap();
+
+        let prompt = run.prompt().unwrap();
+        assert_eq!(prompt.audio_files, vec!["survey_rate_agent"]);
+        assert!(!run.press('7'));
+        assert!(run.retry());
+        let prompt = run.prompt().unwrap();
+        assert_eq!(prompt.audio_files, vec!["survey_invalid", "survey_rate_agent"]);
+
+        // Rating 1 skips the second question
+        assert!(run.press('1'));
+        assert!(run.is_finished());
+        let response = run.response();
+        assert_eq!(response.status, SurveyStatus::Completed);
+        assert_eq!(response.answers, vec![SurveyAnswer { question: 0, digit: 1 }]);
+
+        service.offer("call-2", queue_id, None);
+        let mut run = service.start("call-2", "sip:caller@example.com").unwrap();
+        run.prompt();
+        assert!(run.retry());
+        run.prompt();
+        assert!(!run.retry());
+        assert!(run.is_finished());
+        assert_eq!(run.response().status, SurveyStatus::Partial);
+        assert!(run.thanks_prompt().is_none());
+    }
+
+    #[test]
+    fn test_results_per_question_and_agent() {
+        let (service, queue_id) = service(100);
+        for (call, agent, digits) in [
+            ("call-1", "alice", vec!['5', '1']),
+            ("call-2", "alice", vec!['3', '2']),
+            ("call-3", "bob", vec!['4']),
+        ] {
+            service.offer(call, queue_id, Some(agent.to_string()));
+            let mut run = service.start(call, "sip:caller@example.com").unwrap();
+            for digit in digits {
+                run.prompt();
+                assert!(run.press(digit));
+            }
+            run.abandon();
+            service.record(run.response());
+        }
+
+        let results = service.results(queue_id);
+        assert_eq!(results.responses, 3);
+        assert_eq!(results.completed, 2);
+        assert_eq!(results.partial, 1);
+        assert_eq!(results.questions[0].answers, 3);
+        assert_eq!(results.questions[0].distribution, BTreeMap::from([(3, 1), (4, 1), (5, 1)]));
+        assert_eq!(results.questions[0].average, Some(4.0));
+        assert_eq!(results.questions[1].distribution, BTreeMap::from([(1, 1), (2, 1)]));
+        assert_eq!(results.agents[0].agent, "alice");
+        assert_eq!(results.agents[0].responses, 2);
+        assert_eq!(results.agents[0].average, Some(2.75));
+        assert_eq!(results.agents[1].average, Some(4.0));
+    }
+}
@@ -0,0 +1,722 @@
+//! Notification campaigns
+//!
+//! A campaign calls a list of numbers at a controlled rate, plays an
+//! announcement (or runs an IVR flow) to whoever answers, optionally asks
+//! for a confirmation digit, and records the outcome of every target. Busy
+//! and unanswered targets are retried as the retry policy allows.
+
+use crate::domain::call_forwarding::TimeRange;
+use crate::domain::shared::time_zone::parse_time_zone;
+use chrono::{DateTime, Datelike, Utc};
+use chrono_tz::Tz;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::time::Duration;
+use thiserror::Error;
+use uuid::Uuid;
+
+#[derive(Debug, Error, PartialEq)]
+pub enum CampaignError {
+    #[error("Campaign {0} not found")]
+    NotFound(Uuid),
+    #[error("Invalid campaign: {0}")]
+    Invalid(String),
+    #[error("Campaign is {0}")]
+    InvalidState(CampaignStatus),
+}
+
+/// Number to call, with the variables its prompts are picked with
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct CampaignTarget {
+    pub number: String,
+    #[serde(default)]
+    pub variables: HashMap<String, String>,
+}
+
+impl CampaignTarget {
+    pub fn new(number: &str) -> Self {
+        Self {
+            number: number.to_string(),
+            variables: HashMap::new(),
+        }
+    }
+
+    pub fn with_variable(mut self, name: &str, value: &str) -> Self {
+        self.variables.insert(name.to_string(), value.to_string());
+        self
+    }
+}
+
+/// What an answered call hears
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum CampaignContent {
+    /// Prompts played in order; `{name}` in a prompt is replaced by the
+    /// target's variable, e.g. `reminder-{language}`
+    Announcement { prompts: Vec<String> },
+    /// IVR flow run on the call
+    IvrFlow { flow_id: String },
+}
+
+impl CampaignContent {
+    /// The content with `target`'s variables substituted
+    pub fn for_target(&self, target: &CampaignTarget) -> Self {
+        match self {
+            Self::Announcement { prompts } => Self::Announcement {
+                prompts: prompts
+                    .iter()
+                    .map(|prompt| {
+                        target
+                            .variables
+                            .iter()
+                            .fold(prompt.clone(), |prompt, (name, value)| {
+                                prompt.replace(&format!("{{{}}}", name), value)
+                            })
+                    })
+                    .collect(),
+            },
+            Self::IvrFlow { .. } => self.clone(),
+        }
+    }
+}
+
+/// When busy and unanswered targets are called again
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct RetryPolicy {
+    /// Attempts per target, the first included
+    pub max_attempts: u32,
+    pub retry_delay_secs: u64,
+    pub retry_busy: bool,
+    pub retry_no_answer: bool,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_attempts: 3,
+            retry_delay_secs: 300,
+            retry_busy: true,
+            retry_no_answer: true,
+        }
+    }
+}
+
+fn default_max_concurrent() -> usize {
+    2
+}
+
+fn default_calls_per_second() -> f64 {
+    1.0
+}
+
+fn default_confirm_timeout_secs() -> u64 {
+    10
+}
+
+fn default_machine_hangup_secs() -> u64 {
+    3
+}
+
+/// A campaign as it is created
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct CampaignSettings {
+    pub name: String,
+    /// Number presented to the targets
+    pub caller_id: String,
+    pub targets: Vec<CampaignTarget>,
+    pub content: CampaignContent,
+    /// Digit that confirms the message was heard
+    #[serde(default)]
+    pub confirm_digit: Option<char>,
+    /// How long an answered call waits for the confirmation digit
+    #[serde(default = "default_confirm_timeout_secs")]
+    pub confirm_timeout_secs: u64,
+    /// Hours targets may be called in; any time without
+    #[serde(default)]
+    pub window: Option<TimeRange>,
+    /// Zone of the window (UTC without)
+    #[serde(default)]
+    pub time_zone: Option<String>,
+    #[serde(default = "default_max_concurrent")]
+    pub max_concurrent: usize,
+    #[serde(default = "default_calls_per_second")]
+    pub calls_per_second: f64,
+    #[serde(default)]
+    pub retry: RetryPolicy,
+    /// Trunk the calls go out on; no new call is placed while it is full
+    #[serde(default)]
+    pub trunk: Option<String>,
+    /// A far end hanging up this soon after answering is taken for an
+    /// answering machine
+    #[serde(default = "default_machine_hangup_secs")]
+    pub machine_hangup_secs: u64,
+}
+
+impl CampaignSettings {
+    pub fn new(name: &str, caller_id: &str, targets: Vec<CampaignTarget>, content: CampaignContent) -> Self {
+        Self {
+            name: name.to_string(),
+            caller_id: caller_id.to_string(),
+            targets,
+            content,
+            confirm_digit: None,
+            confirm_timeout_secs: default_confirm_timeout_secs(),
+            window: None,
+            time_zone: None,
+            max_concurrent: default_max_concurrent(),
+            calls_per_second: default_calls_per_second(),
+            retry: RetryPolicy::default(),
+            trunk: None,
+            machine_hangup_secs: default_machine_hangup_secs(),
+        }
+    }
+
+    pub fn validate(&self) -> Result<(), String> {
+        if self.name.trim().is_empty() {
+            return Err("name must not be empty".to_string());
+        }
+        if self.caller_id.trim().is_empty() {
+            return Err("caller_id must not be empty".to_string());
+        }
+        if self.targets.is_empty() {
+            return Err("targets must not be empty".to_string());
+        }
+        if let Some(target) = self.targets.iter().find(|t| t.number.trim().is_empty()) {
+            return Err(format!("target {:?} has no number", target));
+        }
+        match &self.content {
+            CampaignContent::Announcement { prompts } if prompts.is_empty() => {
+                return Err("announcement must have at least one prompt".to_string())
+            }
+            CampaignContent::IvrFlow { flow_id } if flow_id.trim().is_empty() => {
+                return Err("flow_id must not be empty".to_string())
+            }
+            _ => {}
+        }
+        if let Some(digit) = self.confirm_digit {
+            if !matches!(digit, '0'..='9' | '*' | '#') {
+                return Err(format!("confirm_digit '{}' is not a DTMF digit", digit));
+            }
+        }
+        if let Some(zone) = &self.time_zone {
+            parse_time_zone(zone)?;
+        }
+        if self.max_concurrent == 0 {
+            return Err("max_concurrent must be at least 1".to_string());
+        }
+        if !(self.calls_per_second > 0.0 && self.calls_per_second <= 100.0) {
+            return Err("calls_per_second must be above 0 and at most 100".to_string());
+        }
+        if self.retry.max_attempts == 0 {
+            return Err("retry.max_attempts must be at least 1".to_string());
+        }
+        Ok(())
+    }
+
+    /// Time between two calls
+    pub fn pacing(&self) -> Duration {
+        Duration::from_secs_f64(1.0 / self.calls_per_second)
+    }
+
+    /// How long an answered call is kept up for its content
+    pub fn listen_time(&self) -> Duration {
+        let secs = if self.confirm_digit.is_some() {
+            self.confirm_timeout_secs
+        } else {
+            0
+        };
+        Duration::from_secs(secs.max(self.machine_hangup_secs))
+    }
+
+    fn zone(&self) -> Tz {
+        self.time_zone
+            .as_deref()
+            .and_then(|zone| parse_time_zone(zone).ok())
+            .unwrap_or(Tz::UTC)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CampaignStatus {
+    Running,
+    Paused,
+    Cancelled,
+    /// Every target has an outcome
+    Completed,
+}
+
+impl std::fmt::Display for CampaignStatus {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            Self::Running => "running",
+            Self::Paused => "paused",
+            Self::Cancelled => "cancelled",
+            Self::Completed => "completed",
+        };
+        f.write_str(name)
+    }
+}
+
+/// Final result of a target
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "outcome", rename_all = "snake_case")]
+pub enum TargetOutcome {
+    Answered,
+    /// Answered, but hung up too soon for a person who heard the message
+    MachineSuspected,
+    /// Answered and pressed the confirmation digit
+    Confirmed,
+    Failed { cause: String },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TargetStatus {
+    /// Waiting for its (next) attempt
+    Pending,
+    Dialing,
+    Done,
+}
+
+/// Progress of one target
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TargetProgress {
+    pub number: String,
+    pub status: TargetStatus,
+    pub attempts: u32,
+    /// Earliest time of the next attempt after a busy or unanswered one
+    pub next_attempt_at: Option<DateTime<Utc>>,
+    /// Result of the last attempt, e.g. `Busy`
+    pub last_result: Option<String>,
+    pub outcome: Option<TargetOutcome>,
+    /// Call-IDs of the attempts
+    pub call_ids: Vec<String>,
+}
+
+/// What came of dialing a target
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct DialResult {
+    /// Final response to the INVITE; 0 when none was received
+    pub status: u16,
+    /// How long after answering the far end hung up, if it did before the
+    /// content was over
+    pub hangup_after: Option<Duration>,
+    /// Digits pressed during the call
+    pub digits: String,
+    /// Why the call failed, when it did
+    pub cause: Option<String>,
+}
+
+impl DialResult {
+    pub fn answered() -> Self {
+        Self {
+            status: 200,
+            ..Default::default()
+        }
+    }
+
+    pub fn rejected(status: u16) -> Self {
+        Self {
+            status,
+            ..Default::default()
+        }
+    }
+
+    pub fn failed(cause: &str) -> Self {
+        Self {
+            cause: Some(cause.to_string()),
+            ..Default::default()
+        }
+    }
+
+    pub fn with_digits(mut self, digits: &str) -> Self {
+        self.digits = digits.to_string();
+        self
+    }
+
+    pub fn with_hangup_after(mut self, after: Duration) -> Self {
+        self.hangup_after = Some(after);
+        self
+    }
+
+    pub fn is_answered(&self) -> bool {
+        (200..300).contains(&self.status)
+    }
+
+    pub fn is_busy(&self) -> bool {
+        matches!(self.status, 486 | 600)
+    }
+
+    pub fn is_no_answer(&self) -> bool {
+        matches!(self.status, 408 | 480 | 487)
+    }
+}
+
+/// Progress and tallies of a campaign
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CampaignReport {
+    pub id: Uuid,
+    pub name: String,
+    pub status: CampaignStatus,
+    pub created_at: DateTime<Utc>,
+    pub finished_at: Option<DateTime<Utc>>,
+    pub total: usize,
+    pub pending: usize,
+    pub dialing: usize,
+    pub answered: usize,
+    pub machine_suspected: usize,
+    pub confirmed: usize,
+    pub failed: usize,
+    /// Calls placed, retries included
+    pub attempts: u32,
+    pub targets: Vec<TargetProgress>,
+}
+
+/// A campaign and the progress of its targets
+#[derive(Debug, Clone)]
+pub struct Campaign {
+    pub id: Uuid,
+    pub settings: CampaignSettings,
+    pub status: CampaignStatus,
+    pub created_at: DateTime<Utc>,
+    pub finished_at: Option<DateTime<Utc>>,
+    pub targets: Vec<TargetProgress>,
+}
+
+impl Campaign {
+    pub fn new(settings: CampaignSettings) -> Result<Self, CampaignError> {
+        settings.validate().map_err(CampaignError::Invalid)?;
+        let targets = settings
+            .targets
+            .iter()
+            .map(|target| TargetProgress {
+                number: target.number.clone(),
+                status: TargetStatus::Pending,
+                attempts: 0,
+                next_attempt_at: None,
+                last_result: None,
+                outcome: None,
+                call_ids: Vec::new(),
+            })
+            .collect();
+        Ok(Self {
+            id: Uuid::new_v4(),
+            settings,
+            status: CampaignStatus::Running,
+            created_at: Utc::now(),
+            finished_at: None,
+            targets,
+        })
+    }
+
+    /// Correlation ID linking the CDRs of every call of the campaign
+    pub fn correlation_id(&self) -> String {
+        format!("campaign-{}", self.id)
+    }
+
+    pub fn is_finished(&self) -> bool {
+        matches!(self.status, CampaignStatus::Cancelled | CampaignStatus::Completed)
+    }
+
+    /// Whether `now` is within the calling window
+    pub fn in_window(&self, now: DateTime<Utc>) -> bool {
+        match &self.settings.window {
+            Some(window) => {
+                let local = now.with_timezone(&self.settings.zone());
+                window.contains(local.time(), local.weekday())
+            }
+            None => true,
+        }
+    }
+
+    /// Calls in progress
+    pub fn dialing(&self) -> usize {
+        self.targets
+            .iter()
+            .filter(|t| t.status == TargetStatus::Dialing)
+            .count()
+    }
+
+    /// Index of the next target to call at `now`, if one may be called
+    pub fn next_due(&self, now: DateTime<Utc>) -> Option<usize> {
+        if self.status != CampaignStatus::Running
+            || !self.in_window(now)
+            || self.dialing() >= self.settings.max_concurrent
+        {
+            return None;
+        }
+        self.targets.iter().position(|t| {
+            t.status == TargetStatus::Pending && t.next_attempt_at.is_none_or(|at| at <= now)
+        })
+    }
+
+    /// Start the next attempt on target `index`; returns its Call-ID
+    pub fn start_attempt(&mut self, index: usize) -> String {
+        let correlation_id = self.correlation_id();
+        let target = &mut self.targets[index];
+        target.status = TargetStatus::Dialing;
+        target.attempts += 1;
+        target.next_attempt_at = None;
+        let call_id = format!("{}-{}-{}", correlation_id, index, target.attempts);
+        target.call_ids.push(call_id.clone());
+        call_id
+    }
+
+    /// Record what came of the attempt on target `index`
+    pub fn finish_attempt(&mut self, index: usize, result: &DialResult, now: DateTime<Utc>) {
+        let settings = &self.settings;
+        let target = &mut self.targets[index];
+        let (outcome, retry) = if result.is_answered() {
+            let confirmed = settings
+                .confirm_digit
+                .is_some_and(|digit| result.digits.contains(digit));
+            let early_hangup = result
+                .hangup_after
+                .is_some_and(|after| after < Duration::from_secs(settings.machine_hangup_secs));
+            let outcome = if confirmed {
+                TargetOutcome::Confirmed
+            } else if early_hangup {
+                TargetOutcome::MachineSuspected
+            } else {
+                TargetOutcome::Answered
+            };
+            (outcome, false)
+        } else {
+            let cause = if result.is_busy() {
+                "Busy".to_string()
+            } else if result.is_no_answer() {
+                "No answer".to_string()
+            } else {
+                match (&result.cause, result.status) {
+                    (Some(cause), _) => cause.clone(),
+                    (None, 0) => "Unreachable".to_string(),
+                    (None, status) => format!("Rejected with {}", status),
+                }
+            };
+            let retry = (result.is_busy() && settings.retry.retry_busy)
+                || (result.is_no_answer() && settings.retry.retry_no_answer);
+            (TargetOutcome::Failed { cause }, retry)
+        };
+
+        target.last_result = Some(match &outcome {
+            TargetOutcome::Failed { cause } => cause.clone(),
+            outcome => format!("{:?}", outcome),
+        });
+        if retry && target.attempts < settings.retry.max_attempts {
+            target.status = TargetStatus::Pending;
+            target.next_attempt_at =
+                Some(now + chrono::Duration::seconds(settings.retry.retry_delay_secs as i64));
+        } else {
+            target.status = TargetStatus::Done;
+            target.outcome = Some(outcome);
+        }
+
+        if !self.is_finished() && self.targets.iter().all(|t| t.status == TargetStatus::Done) {
+            self.status = CampaignStatus::Completed;
+            self.finished_at = Some(now);
+        }
+    }
+
+    /// Stop placing calls; calls in progress go on
+    pub fn pause(&mut self) -> Result<(), CampaignError> {
+        match self.status {
+            CampaignStatus::Running => {
+                self.status = CampaignStatus::Paused;
+                Ok(())
+            }
+            status => Err(CampaignError::InvalidState(status)),
+        }
+    }
+
+    pub fn resume(&mut self) -> Result<(), CampaignError> {
+        match self.status {
+            CampaignStatus::Paused => {
+                self.status = CampaignStatus::Running;
+                Ok(())
+            }
+            status => Err(CampaignError::InvalidState(status)),
+        }
+    }
+
+    /// End the campaign; targets not called yet stay pending
+    pub fn cancel(&mut self, now: DateTime<Utc>) -> Result<(), CampaignError> {
+        match self.status {
+            CampaignStatus::Running | CampaignStatus::Paused => {
+                self.status = CampaignStatus::Cancelled;
+                self.finished_at = Some(now);
+                Ok(())
+            }
+            status => Err(CampaignError::InvalidState(status)),
+        }
+    }
+
+    pub fn report(&self) -> CampaignReport {
+        let count = |f: &dyn Fn(&TargetProgress) -> bool| self.targets.iter().filter(|t| f(t)).count();
+        let outcome = |wanted: fn(&TargetOutcome) -> bool| {
+            count(&|t: &TargetProgress| t.outcome.as_ref().is_some_and(wanted))
+        };
+        CampaignReport {
+            id: self.id,
+            name: self.settings.name.clone(),
+            status: self.status,
+            created_at: self.created_at,
+            finished_at: self.finished_at,
+            total: self.targets.len(),
+            pending: count(&|t| t.status == TargetStatus::Pending),
+            dialing: count(&|t| t.status == TargetStatus::Dialing),
+            answered: outcome(|o| *o == TargetOutcome::Answered),
+            machine_suspected: outcome(|o| *o == TargetOutcome::MachineSuspected),
+            confirmed: outcome(|o| *o == TargetOutcome::Confirmed),
+            failed: outcome(|o| matches!(o, TargetOutcome::Failed { .. })),
+            attempts: self.targets.iter().map(|t| t.attempts).sum(),
+            targets: self.targets.clone(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::{NaiveTime, TimeZone, Weekday};
+
+    fn settings() -> CampaignSettings {
+        CampaignSettings::new(
+            "Closure notice",
+            "5000",
+            vec![
+                CampaignTarget::new("15550101").with_variable("language", "en"),
+                CampaignTarget::new("15550102").with_variable("language", "es"),
+            ],
+            CampaignContent::Announcement {
+                prompts: vec!["closure-{language}".to_string()],
+            },
+        )
+    }
+
+    #[test]
+    fn test_validate_settings() {
+        assert!(settings().validate().is_ok());
+
+        let mut invalid = settings();
+        invalid.targets.clear();
+        assert!(invalid.validate().is_err());
+
+        let mut invalid = settings();
+        invalid.calls_per_second = 0.0;
+        assert!(invalid.validate().is_err());
+
+        let mut invalid = settings();
+        invalid.confirm_digit = Some('x');
+        assert!(invalid.validate().is_err());
+
+        let mut invalid = settings();
+        invalid.time_zone = Some("Mars/Olympus".to_string());
+        assert!(matches!(Campaign::new(invalid), Err(CampaignError::Invalid(_))));
+    }
+
+    #[test]
+    fn test_content_for_target() {
+        let settings = settings();
+        assert_eq!(
+            settings.content.for_target(&settings.targets[1]),
+            CampaignContent::Announcement {
+                prompts: vec!["closure-es".to_string()]
+            }
+        );
+    }
+
+    #[test]
+    fn test_outcomes_and_retries() {
+        let mut settings = settings();
+        settings.confirm_digit = Some('1');
+        settings.targets.push(CampaignTarget::new("15550103"));
+        settings.targets.push(CampaignTarget::new("15550104"));
+        settings.retry.max_attempts = 2;
+        let mut campaign = Campaign::new(settings).unwrap();
+        let now = Utc::now();
+
+        // Busy is retried after the delay, then gives up
+        let first = campaign.next_due(now).unwrap();
+        assert_eq!(first, 0);
+        let call_id = campaign.start_attempt(first);
+        assert_eq!(call_id, format!("{}-0-1", campaign.correlation_id()));
+        campaign.finish_attempt(first, &DialResult::rejected(486), now);
+        assert_eq!(campaign.targets[0].status, TargetStatus::Pending);
+        assert_eq!(campaign.next_due(now), Some(1));
+
+        campaign.start_attempt(1);
+        campaign.finish_attempt(1, &DialResult::answered().with_digits("1"), now);
+        campaign.start_attempt(2);
+        campaign.finish_attempt(
+            2,
+            &DialResult::answered().with_hangup_after(Duration::from_secs(1)),
+            now,
+        );
+        campaign.start_attempt(3);
+        campaign.finish_attempt(3, &DialResult::rejected(404), now);
+
+        // Only the busy target is left, not due yet
+        assert_eq!(campaign.next_due(now), None);
+        let later = now + chrono::Duration::seconds(300);
+        assert_eq!(campaign.next_due(later), Some(0));
+        campaign.start_attempt(0);
+        campaign.finish_attempt(0, &DialResult::rejected(486), later);
+
+        let report = campaign.report();
+        assert_eq!(report.status, CampaignStatus::Completed);
+        assert_eq!(report.confirmed, 1);
+        assert_eq!(report.machine_suspected, 1);
+        assert_eq!(report.failed, 2);
+        assert_eq!(report.attempts, 5);
+        assert_eq!(
+            campaign.targets[3].outcome,
+            Some(TargetOutcome::Failed {
+                cause: "Rejected with 404".to_string()
+            })
+        );
+        assert_eq!(campaign.targets[0].call_ids.len(), 2);
+    }
+
+    #[test]
+    fn test_pause_resume_cancel() {
+        let mut campaign = Campaign::new(settings()).unwrap();
+        let now = Utc::now();
+
+        campaign.pause().unwrap();
+        assert_eq!(campaign.next_due(now), None);
+        assert_eq!(campaign.pause(), Err(CampaignError::InvalidState(CampaignStatus::Paused)));
+        campaign.resume().unwrap();
+        assert_eq!(campaign.next_due(now), Some(0));
+
+        campaign.cancel(now).unwrap();
+        assert!(campaign.is_finished());
+        assert_eq!(campaign.next_due(now), None);
+        assert!(campaign.resume().is_err());
+        assert_eq!(campaign.report().pending, 2);
+    }
+
+    #[test]
+    fn test_calling_window() {
+        let mut settings = settings();
+        settings.window = Some(
+            TimeRange::new(
+                NaiveTime::from_hms_opt(9, 0, 0).unwrap(),
+                NaiveTime::from_hms_opt(17, 0, 0).unwrap(),
+            )
+            .with_days(vec![Weekday::Mon, Weekday::Tue, Weekday::Wed, Weekday::Thu, Weekday::Fri]),
+        );
+        settings.time_zone = Some("America/New_York".to_string());
+        let campaign = Campaign::new(settings).unwrap();
+
+        // Monday 2024-03-04: 15:00 UTC is 10:00 in New York, 23:00 UTC is 18:00
+        let morning = Utc.with_ymd_and_hms(2024, 3, 4, 15, 0, 0).unwrap();
+        let evening = Utc.with_ymd_and_hms(2024, 3, 4, 23, 0, 0).unwrap();
+        let saturday = Utc.with_ymd_and_hms(2024, 3, 9, 15, 0, 0).unwrap();
+        assert!(campaign.in_window(morning));
+        assert!(!campaign.in_window(evening));
+        assert!(!campaign.in_window(saturday));
+        assert_eq!(campaign.next_due(evening), None);
+    }
+}
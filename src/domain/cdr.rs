@@ -2,6 +2,8 @@
 //!
 //! CDR captures information about each call for billing, auditing, and analytics.
 
+use crate::domain::billing::SupervisionPoint;
+use crate::domain::shared::SortOrder;
 use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
 use uuid::Uuid;
@@ -33,7 +35,16 @@ pub struct CallDetailRecord {
     pub answer_time: Option<DateTime<Utc>>,
     pub end_time: Option<DateTime<Utc>>,
 
-    /// Duration in seconds
+    /// First early media (183 with SDP) from the callee; never billed from
+    #[serde(default)]
+    pub early_media_time: Option<DateTime<Utc>>,
+
+    /// ACK of the 2xx completing the answer
+    #[serde(default)]
+    pub ack_time: Option<DateTime<Utc>>,
+
+    /// Duration in seconds; setup runs from the INVITE to the first
+    /// provisional response (to the answer when there was none)
     pub setup_duration: Option<i32>,
     pub call_duration: Option<i32>,
     pub total_duration: Option<i32>,
@@ -50,11 +61,90 @@ pub struct CallDetailRecord {
     pub rtp_bytes_sent: Option<i64>,
     pub rtp_bytes_received: Option<i64>,
 
+    /// Shared by all legs of a forwarded/transferred call chain
+    #[serde(default)]
+    pub correlation_id: Option<String>,
+
+    /// Number as dialed when it was rewritten (e.g. a speed dial code);
+    /// `callee_uri` holds the resolved destination
+    #[serde(default)]
+    pub dialed_number: Option<String>,
+
+    /// Number of 3xx redirects followed to reach `callee_uri`
+    #[serde(default)]
+    pub redirect_count: i32,
+
+    /// Seconds the call spent on hold, over all hold segments
+    #[serde(default)]
+    pub hold_duration: i32,
+
+    /// Number of times the call was put on hold
+    #[serde(default)]
+    pub hold_count: i32,
+
+    /// Call to a built-in test service; never billed
+    #[serde(default)]
+    pub test_call: bool,
+
+    /// Message variant played by an announcement-only number ("default" or
+    /// the 1-based position of its time-conditioned message)
+    #[serde(default)]
+    pub announcement_variant: Option<String>,
+
+    /// Trunk the call came in from or went out through
+    #[serde(default)]
+    pub trunk: Option<String>,
+
+    /// Answered and hung up within the trunk's answer validation window,
+    /// like a carrier-side announcement or voicemail
+    #[serde(default)]
+    pub suspect_answer: bool,
+
+    /// Captioned live while in progress; kept for compliance review
+    #[serde(default)]
+    pub live_transcribed: bool,
+
+    /// Why media fell back from Opus to G.711 mid-call, if it did
+    #[serde(default)]
+    pub codec_fallback: Option<String>,
+
+    /// First provisional response (180 or 183) from the callee
+    #[serde(default)]
+    pub ring_time: Option<DateTime<Utc>>,
+
+    /// Seconds from the first provisional response to the answer, or to
+    /// the end of a call never answered
+    #[serde(default)]
+    pub ring_duration: Option<i32>,
+
+    /// Seconds answered and not on hold; 0 for calls never answered
+    #[serde(default)]
+    pub talk_duration: Option<i32>,
+
+    /// Milliseconds from the INVITE to the caller authenticated
+    #[serde(default)]
+    pub auth_latency_ms: Option<i32>,
+
+    /// Milliseconds from the INVITE to the routing decision
+    #[serde(default)]
+    pub routing_latency_ms: Option<i32>,
+
+    /// Milliseconds from the INVITE to the first 180/183 sent
+    #[serde(default)]
+    pub provisional_latency_ms: Option<i32>,
+
+    /// Milliseconds from the INVITE to the answer
+    #[serde(default)]
+    pub answer_latency_ms: Option<i32>,
+
     /// Metadata
     pub created_at: DateTime<Utc>,
     pub updated_at: DateTime<Utc>,
 }
 
+/// End reason recorded when a call was diverted to voicemail
+pub const END_REASON_VOICEMAIL: &str = "voicemail";
+
 /// Call direction
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
 #[serde(rename_all = "lowercase")]
@@ -95,6 +185,13 @@ pub enum CallStatus {
     Cancelled,
     /// Call was rejected/declined
     Rejected,
+    /// Call was sent on by a queue overflow rule
+    Overflowed,
+    /// Call was answered by an announcement-only number
+    Announcement,
+    /// Call was refused by the caller's class of service
+    #[serde(rename = "blocked-cos")]
+    BlockedCos,
 }
 
 impl CallStatus {
@@ -107,6 +204,9 @@ impl CallStatus {
             CallStatus::NoAnswer => "no_answer",
             CallStatus::Cancelled => "cancelled",
             CallStatus::Rejected => "rejected",
+            CallStatus::Overflowed => "overflowed",
+            CallStatus::Announcement => "announcement",
+            CallStatus::BlockedCos => "blocked-cos",
         }
     }
 
@@ -119,6 +219,9 @@ impl CallStatus {
             "no_answer" => Some(CallStatus::NoAnswer),
             "cancelled" => Some(CallStatus::Cancelled),
             "rejected" => Some(CallStatus::Rejected),
+            "overflowed" => Some(CallStatus::Overflowed),
+            "announcement" => Some(CallStatus::Announcement),
+            "blocked-cos" => Some(CallStatus::BlockedCos),
             _ => None,
         }
     }
@@ -149,6 +252,8 @@ impl CallDetailRecord {
             start_time: now,
             answer_time: None,
             end_time: None,
+            early_media_time: None,
+            ack_time: None,
             setup_duration: None,
             call_duration: None,
             total_duration: None,
@@ -160,6 +265,24 @@ impl CallDetailRecord {
             rtp_packets_received: None,
             rtp_bytes_sent: None,
             rtp_bytes_received: None,
+            correlation_id: None,
+            dialed_number: None,
+            redirect_count: 0,
+            hold_duration: 0,
+            hold_count: 0,
+            test_call: false,
+            announcement_variant: None,
+            trunk: None,
+            suspect_answer: false,
+            live_transcribed: false,
+            codec_fallback: None,
+            ring_time: None,
+            ring_duration: None,
+            talk_duration: None,
+            auth_latency_ms: None,
+            routing_latency_ms: None,
+            provisional_latency_ms: None,
+            answer_latency_ms: None,
             created_at: now,
             updated_at: now,
         }
@@ -167,26 +290,109 @@ impl CallDetailRecord {
 
     /// Mark the call as answered
     pub fn mark_answered(&mut self) {
-        let now = Utc::now();
-        self.answer_time = Some(now);
-        self.setup_duration = Some((now - self.start_time).num_seconds() as i32);
-        self.updated_at = now;
+        self.mark_answered_at(Utc::now());
+    }
+
+    /// Mark the call as answered at `at`
+    pub fn mark_answered_at(&mut self, at: DateTime<Utc>) {
+        self.answer_time = Some(at);
+        match self.ring_time {
+            Some(ring_time) => self.ring_duration = Some((at - ring_time).num_seconds() as i32),
+            None => self.setup_duration = Some((at - self.start_time).num_seconds() as i32),
+        }
+        self.updated_at = Utc::now();
+    }
+
+    /// Record the first provisional response of the callee, which ends
+    /// call setup
+    pub fn mark_ringing(&mut self, at: DateTime<Utc>) {
+        if self.ring_time.is_none() && self.answer_time.is_none() {
+            self.ring_time = Some(at);
+            self.setup_duration = Some((at - self.start_time).num_seconds() as i32);
+            self.updated_at = Utc::now();
+        }
+    }
+
+    /// Record the first early media of the call
+    pub fn mark_early_media(&mut self, at: DateTime<Utc>) {
+        if self.early_media_time.is_none() {
+            self.early_media_time = Some(at);
+            self.updated_at = Utc::now();
+        }
+    }
+
+    /// Record the ACK completing the answer (retransmissions keep the first)
+    pub fn mark_ack_complete(&mut self, at: DateTime<Utc>) {
+        if self.ack_time.is_none() {
+            self.ack_time = Some(at);
+            self.updated_at = Utc::now();
+        }
     }
 
     /// Mark the call as ended
     pub fn mark_ended(&mut self, status: CallStatus, reason: Option<String>, response_code: Option<u16>) {
-        let now = Utc::now();
-        self.end_time = Some(now);
+        self.mark_ended_at(status, reason, response_code, Utc::now());
+    }
+
+    /// Mark the call as ended at `at`
+    ///
+    /// Talk time is the answered time less the hold time recorded so far,
+    /// so a running hold segment must be added first.
+    pub fn mark_ended_at(
+        &mut self,
+        status: CallStatus,
+        reason: Option<String>,
+        response_code: Option<u16>,
+        at: DateTime<Utc>,
+    ) {
+        self.end_time = Some(at);
         self.status = status;
         self.end_reason = reason;
         self.sip_response_code = response_code;
-        self.total_duration = Some((now - self.start_time).num_seconds() as i32);
-
-        if let Some(answer_time) = self.answer_time {
-            self.call_duration = Some((now - answer_time).num_seconds() as i32);
+        self.total_duration = Some((at - self.start_time).num_seconds() as i32);
+
+        match self.answer_time {
+            Some(answer_time) => {
+                let call_duration = (at - answer_time).num_seconds() as i32;
+                self.call_duration = Some(call_duration);
+                self.talk_duration = Some((call_duration - self.hold_duration).max(0));
+            }
+            None => {
+                self.talk_duration = Some(0);
+                if let Some(ring_time) = self.ring_time {
+                    self.ring_duration = Some((at - ring_time).num_seconds() as i32);
+                }
+            }
         }
 
-        self.updated_at = now;
+        self.updated_at = Utc::now();
+    }
+
+    /// Record that a queue overflow rule sent the call to `destination`
+    ///
+    /// The status stays overflowed when the call ends.
+    pub fn mark_overflowed(&mut self, destination: String) {
+        self.status = CallStatus::Overflowed;
+        self.end_reason = Some(format!("Queue overflow to {}", destination));
+        self.updated_at = Utc::now();
+    }
+
+    /// Record that an announcement-only number played `variant`
+    ///
+    /// The status stays announcement when the call ends.
+    pub fn mark_announcement(&mut self, variant: String) {
+        self.status = CallStatus::Announcement;
+        self.announcement_variant = Some(variant);
+        self.updated_at = Utc::now();
+    }
+
+    /// Record that the caller's class of service does not allow the call
+    ///
+    /// The status stays blocked-cos when the call ends.
+    pub fn mark_blocked_cos(&mut self, reason: String) {
+        self.status = CallStatus::BlockedCos;
+        self.end_reason = Some(reason);
+        self.updated_at = Utc::now();
     }
 
     /// Set media information
@@ -206,11 +412,119 @@ impl CallDetailRecord {
         self.updated_at = Utc::now();
     }
 
+    /// Link this CDR to the other legs of a forwarded/transferred call chain
+    pub fn set_correlation_id(&mut self, correlation_id: String) {
+        self.correlation_id = Some(correlation_id);
+        self.updated_at = Utc::now();
+    }
+
+    /// Record the number the caller dialed before it was resolved
+    pub fn set_dialed_number(&mut self, dialed: String) {
+        self.dialed_number = Some(dialed);
+        self.updated_at = Utc::now();
+    }
+
+    /// Record the target reached after following redirects
+    pub fn set_redirected(&mut self, callee_uri: String, redirect_count: i32) {
+        self.set_callee(callee_uri);
+        self.redirect_count = redirect_count;
+    }
+
+    /// Record another party taking the callee's place, e.g. a pickup
+    pub fn set_callee(&mut self, callee_uri: String) {
+        self.callee_username = callee_uri
+            .trim_start_matches("sips:")
+            .trim_start_matches("sip:")
+            .split('@')
+            .next()
+            .unwrap_or_default()
+            .to_string();
+        self.callee_uri = callee_uri;
+        self.updated_at = Utc::now();
+    }
+
+    /// Record the start of a hold segment
+    pub fn mark_held(&mut self) {
+        self.hold_count += 1;
+        self.updated_at = Utc::now();
+    }
+
+    /// Add the length of a finished hold segment
+    pub fn add_hold_time(&mut self, seconds: i32) {
+        self.hold_duration += seconds.max(0);
+        self.updated_at = Utc::now();
+    }
+
+    /// Record that the call reached a built-in test service
+    pub fn mark_test_call(&mut self) {
+        self.test_call = true;
+        self.updated_at = Utc::now();
+    }
+
+    /// Whether the call is charged for: answered, and not a test call
+    pub fn is_billable(&self) -> bool {
+        !self.test_call && self.answer_time.is_some()
+    }
+
+    /// Seconds billed when billing from `point`; `None` when the call never
+    /// reached it
+    pub fn billable_seconds(&self, point: SupervisionPoint) -> Option<i32> {
+        match point {
+            SupervisionPoint::Answer => self
+                .answer_time
+                .map(|_| self.call_duration.unwrap_or(0).max(0)),
+            SupervisionPoint::AckComplete => self.ack_time.map(|ack| {
+                self.end_time
+                    .map_or(0, |end| (end - ack).num_seconds().max(0) as i32)
+            }),
+        }
+    }
+
+    /// Record the trunk the call used
+    pub fn set_trunk(&mut self, trunk: String) {
+        self.trunk = Some(trunk);
+        self.updated_at = Utc::now();
+    }
+
+    /// Flag the answer as suspect (hung up right after answering)
+    pub fn flag_suspect_answer(&mut self) {
+        self.suspect_answer = true;
+        self.updated_at = Utc::now();
+    }
+
+    /// Record that the call was captioned live
+    pub fn mark_live_transcribed(&mut self) {
+        self.live_transcribed = true;
+        self.updated_at = Utc::now();
+    }
+
+    /// Record that media fell back to another codec mid-call, and why
+    pub fn record_codec_fallback(&mut self, note: String) {
+        self.codec_fallback = Some(note);
+        self.updated_at = Utc::now();
+    }
+
     /// Set callee IP address
     pub fn set_callee_ip(&mut self, ip: String) {
         self.callee_ip = Some(ip);
         self.updated_at = Utc::now();
     }
+
+    /// Record the milliseconds from the INVITE to authentication, the
+    /// routing decision, the first 180/183 and the answer
+    pub fn set_setup_latency(
+        &mut self,
+        auth_ms: Option<i32>,
+        routing_ms: Option<i32>,
+        provisional_ms: Option<i32>,
+        answer_ms: Option<i32>,
+    ) {
+        self.auth_latency_ms = auth_ms;
+        self.routing_latency_ms = routing_ms;
+        self.provisional_latency_ms = provisional_ms;
+        self.answer_latency_ms = answer_ms;
+        self.updated_at = Utc::now();
+    }
 }
 
 /// CDR Repository trait
@@ -242,6 +556,16 @@ pub trait CdrRepository: Send + Sync {
 
     /// Delete old CDRs (for cleanup)
     async fn delete_older_than(&self, days: i32) -> Result<i64, String>;
+
+    /// List the most recent CDRs where the user is caller or callee, newest first
+    ///
+    /// Also includes the other legs of any call chain (same correlation id)
+    /// the user took part in, so history can be grouped per logical call.
+    async fn list_for_user(
+        &self,
+        username: &str,
+        limit: i64,
+    ) -> Result<Vec<CallDetailRecord>, String>;
 }
 
 /// Filters for CDR queries
@@ -254,6 +578,9 @@ pub struct CdrFilters {
     pub start_time_from: Option<DateTime<Utc>>,
     pub start_time_to: Option<DateTime<Utc>>,
     pub min_duration: Option<i32>,
+    /// Order of listed CDRs (start_time, call_duration, caller_username,
+    /// callee_username); newest first when unset
+    pub sort: Option<SortOrder>,
 }
 
 #[cfg(test)]
@@ -520,6 +847,9 @@ mod tests {
         assert_eq!(CallStatus::NoAnswer.as_str(), "no_answer");
         assert_eq!(CallStatus::Cancelled.as_str(), "cancelled");
         assert_eq!(CallStatus::Rejected.as_str(), "rejected");
+        assert_eq!(CallStatus::Overflowed.as_str(), "overflowed");
+        assert_eq!(CallStatus::Announcement.as_str(), "announcement");
+        assert_eq!(CallStatus::BlockedCos.as_str(), "blocked-cos");
 
         // Test from_str for all variants
         assert_eq!(CallStatus::from_str("active"), Some(CallStatus::Active));
@@ -529,6 +859,9 @@ mod tests {
         assert_eq!(CallStatus::from_str("no_answer"), Some(CallStatus::NoAnswer));
         assert_eq!(CallStatus::from_str("cancelled"), Some(CallStatus::Cancelled));
         assert_eq!(CallStatus::from_str("rejected"), Some(CallStatus::Rejected));
+        assert_eq!(CallStatus::from_str("overflowed"), Some(CallStatus::Overflowed));
+        assert_eq!(CallStatus::from_str("announcement"), Some(CallStatus::Announcement));
+        assert_eq!(CallStatus::from_str("blocked-cos"), Some(CallStatus::BlockedCos));
         assert_eq!(CallStatus::from_str("unknown"), None);
     }
 }
@@ -0,0 +1,274 @@
+//! CDR retention
+//!
+//! Each tenant (identified by SIP realm, as in branding) keeps full CDRs
+//! for `full_days`. After that a CDR is anonymized — parties, addresses and
+//! Call-ID removed, times, durations and outcome kept for aggregate
+//! reporting — until `aggregate_days`, when it is deleted. With no
+//! aggregate period CDRs are deleted as soon as their full period ends.
+
+use crate::domain::cdr::CallDetailRecord;
+use crate::domain::tenant_branding::realm_of;
+use async_trait::async_trait;
+use chrono::{DateTime, Duration, NaiveDate, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use uuid::Uuid;
+
+/// How long CDRs of a tenant are kept
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RetentionPolicy {
+    /// Days a CDR is kept in full
+    pub full_days: u32,
+    /// Days an anonymized CDR is kept for aggregates (counted from the
+    /// call start, like `full_days`); 0 deletes CDRs after `full_days`
+    #[serde(default)]
+    pub aggregate_days: u32,
+}
+
+impl Default for RetentionPolicy {
+    fn default() -> Self {
+        Self {
+            full_days: 365,
+            aggregate_days: 0,
+        }
+    }
+}
+
+/// What retention does with one CDR
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RetentionAction {
+    Keep,
+    Anonymize,
+    Delete,
+}
+
+impl RetentionPolicy {
+    /// Action for a CDR of a call started at `start_time`
+    pub fn action(
+        &self,
+        start_time: DateTime<Utc>,
+        anonymized: bool,
+        now: DateTime<Utc>,
+    ) -> RetentionAction {
+        let age = now - start_time;
+        if age < Duration::days(self.full_days as i64) {
+            RetentionAction::Keep
+        } else if self.aggregate_days > self.full_days
+            && age < Duration::days(self.aggregate_days as i64)
+        {
+            if anonymized {
+                RetentionAction::Keep
+            } else {
+                RetentionAction::Anonymize
+            }
+        } else {
+            RetentionAction::Delete
+        }
+    }
+}
+
+/// Monthly partitions of the CDR table, created ahead of time
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct CdrPartitioningConfig {
+    /// Only takes effect once the table was converted with
+    /// `partition_call_records()`
+    pub enabled: bool,
+    /// Months after the current one to create partitions for
+    pub months_ahead: u32,
+}
+
+impl Default for CdrPartitioningConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            months_ahead: 3,
+        }
+    }
+}
+
+/// CDR retention settings
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct CdrRetentionConfig {
+    /// Run the maintenance task on a schedule (manual runs always work)
+    pub enabled: bool,
+    /// Seconds between scheduled runs
+    pub interval_secs: u64,
+    /// CDRs examined per batch
+    pub batch_size: u32,
+    /// Pause between batches, so maintenance does not starve the pool
+    pub batch_pause_ms: u64,
+    /// Policy of CDRs not belonging to a configured tenant
+    pub default_policy: RetentionPolicy,
+    /// Per-tenant policies, keyed by SIP realm
+    pub tenants: HashMap<String, RetentionPolicy>,
+    /// Directory expiring CDRs are written to (gzip'd JSONL) before they
+    /// are deleted or anonymized; nothing is archived when unset
+    pub archive_dir: Option<String>,
+    pub partitioning: CdrPartitioningConfig,
+}
+
+impl Default for CdrRetentionConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            interval_secs: 86400,
+            batch_size: 500,
+            batch_pause_ms: 200,
+            default_policy: RetentionPolicy::default(),
+            tenants: HashMap::new(),
+            archive_dir: None,
+            partitioning: CdrPartitioningConfig::default(),
+        }
+    }
+}
+
+impl CdrRetentionConfig {
+    /// Policy of a CDR: its caller's tenant, else its callee's, else the default
+    pub fn policy_for(&self, cdr: &CallDetailRecord) -> &RetentionPolicy {
+        [cdr.caller_uri.as_str(), cdr.callee_uri.as_str()]
+            .into_iter()
+            .filter_map(realm_of)
+            .find_map(|realm| self.tenants.get(realm))
+            .unwrap_or(&self.default_policy)
+    }
+
+    /// Calls started before this may have expired under some policy
+    pub fn earliest_cutoff(&self, now: DateTime<Utc>) -> DateTime<Utc> {
+        let days = self
+            .tenants
+            .values()
+            .chain(std::iter::once(&self.default_policy))
+            .map(|policy| policy.full_days)
+            .min()
+            .unwrap_or_default();
+        now - Duration::days(days as i64)
+    }
+}
+
+/// A CDR old enough to be looked at by retention
+#[derive(Debug, Clone)]
+pub struct RetentionCandidate {
+    pub cdr: CallDetailRecord,
+    /// Already anonymized by an earlier run
+    pub anonymized: bool,
+}
+
+/// Outcome of a retention run
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct CdrRetentionReport {
+    /// Nothing was changed; counts are what would have happened
+    pub dry_run: bool,
+    pub examined: u64,
+    pub deleted: u64,
+    pub anonymized: u64,
+    /// Archive files written
+    pub archives: Vec<String>,
+    /// Partitions created ahead of time
+    pub partitions_created: Vec<String>,
+    pub duration_ms: u64,
+}
+
+/// Storage operations of CDR retention
+#[cfg_attr(test, mockall::automock)]
+#[async_trait]
+pub trait CdrRetentionRepository: Send + Sync {
+    /// CDRs of calls started before `before`, oldest first, after the
+    /// `(start_time, id)` position `after`
+    async fn list_started_before(
+        &self,
+        before: DateTime<Utc>,
+        after: Option<(DateTime<Utc>, Uuid)>,
+        limit: i64,
+    ) -> Result<Vec<RetentionCandidate>, String>;
+
+    async fn delete(&self, ids: &[Uuid]) -> Result<u64, String>;
+
+    /// Remove parties, addresses and Call-ID, keeping times and outcome
+    async fn anonymize(&self, ids: &[Uuid]) -> Result<u64, String>;
+
+    /// Create the monthly partition starting at `month`; returns its name,
+    /// or None when it exists already or the table is not partitioned
+    async fn ensure_partition(&self, month: NaiveDate) -> Result<Option<String>, String>;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::domain::cdr::CallDirection;
+
+    fn cdr(caller: &str, callee: &str) -> CallDetailRecord {
+        CallDetailRecord::new(
+            "call-1".to_string(),
+            "alice".to_string(),
+            caller.to_string(),
+            "192.0.2.1".to_string(),
+            "bob".to_string(),
+            callee.to_string(),
+            CallDirection::Internal,
+        )
+    }
+
+    #[test]
+    fn test_policy_actions() {
+        let now = Utc::now();
+        let policy = RetentionPolicy {
+            full_days: 30,
+            aggregate_days: 90,
+        };
+        let days_ago = |days: i64| now - Duration::days(days);
+
+        assert_eq!(policy.action(days_ago(29), false, now), RetentionAction::Keep);
+        assert_eq!(
+            policy.action(days_ago(30), false, now),
+            RetentionAction::Anonymize
+        );
+        assert_eq!(policy.action(days_ago(60), true, now), RetentionAction::Keep);
+        assert_eq!(policy.action(days_ago(90), true, now), RetentionAction::Delete);
+
+        let no_aggregates = RetentionPolicy {
+            full_days: 30,
+            aggregate_days: 0,
+        };
+        assert_eq!(
+            no_aggregates.action(days_ago(31), false, now),
+            RetentionAction::Delete
+        );
+    }
+
+    #[test]
+    fn test_tenant_policy_by_realm() {
+        let mut config = CdrRetentionConfig::default();
+        let short = RetentionPolicy {
+            full_days: 7,
+            aggregate_days: 0,
+        };
+        config.tenants.insert("acme.example.com".to_string(), short);
+
+        assert_eq!(
+            config.policy_for(&cdr(
+                "sip:alice@acme.example.com",
+                "sip:+15551234@trunk.example.net"
+            )),
+            &short
+        );
+        assert_eq!(
+            config.policy_for(&cdr(
+                "sip:+15551234@trunk.example.net",
+                "<sip:bob@acme.example.com>"
+            )),
+            &short
+        );
+        assert_eq!(
+            config.policy_for(&cdr(
+                "sip:alice@other.example.com",
+                "sip:bob@other.example.com"
+            )),
+            &config.default_policy
+        );
+        let now = Utc::now();
+        assert_eq!(config.earliest_cutoff(now), now - Duration::days(7));
+    }
+}
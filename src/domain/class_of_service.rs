@@ -0,0 +1,739 @@
+//! Classes of service for outbound calls
+//!
+//! Every dialed destination falls in one category (internal, local,
+//! national, mobile, international or premium) by the longest matching
+//! prefix of its tenant's plan. A class of service lists the categories its
+//! users may call; users without an assignment get their tenant's default
+//! class, and an admin can move a user to another class until a given
+//! time. A caller stopped by their class may enter an authorization code,
+//! which grants the code's class for that one call.
+//!
+//! Assignments, overrides and recent code uses live in memory and are
+//! snapshotted to disk like the fraud counters.
+
+use chrono::{DateTime, Utc};
+use metrics::counter;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, VecDeque};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, RwLock};
+use tokio::task::JoinHandle;
+use tracing::{error, info, warn};
+
+/// Number of authorization code uses kept per user for the status endpoint
+const RECENT_CODE_USES: usize = 20;
+
+/// Seconds a blocked caller hears the announcement before the call is ended
+pub const ANNOUNCEMENT_SECS: u64 = 10;
+
+/// Longest authorization code read from a caller
+pub const MAX_CODE_DIGITS: usize = 16;
+
+/// Kind of destination a call goes to
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DestinationCategory {
+    Internal,
+    Local,
+    National,
+    Mobile,
+    International,
+    Premium,
+}
+
+impl DestinationCategory {
+    pub const ALL: [DestinationCategory; 6] = [
+        DestinationCategory::Internal,
+        DestinationCategory::Local,
+        DestinationCategory::National,
+        DestinationCategory::Mobile,
+        DestinationCategory::International,
+        DestinationCategory::Premium,
+    ];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            DestinationCategory::Internal => "internal",
+            DestinationCategory::Local => "local",
+            DestinationCategory::National => "national",
+            DestinationCategory::Mobile => "mobile",
+            DestinationCategory::International => "international",
+            DestinationCategory::Premium => "premium",
+        }
+    }
+}
+
+/// How a tenant's dialed numbers map to categories
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct CategoryPlan {
+    /// Dialed digit strings up to this length are internal extensions
+    pub internal_max_digits: usize,
+    /// Category by dialed prefix; the longest matching prefix wins
+    pub prefixes: HashMap<String, DestinationCategory>,
+    /// Category of external numbers no prefix matches
+    pub unmatched: DestinationCategory,
+}
+
+impl Default for CategoryPlan {
+    /// North American dialing; mobile numbers share area codes there, so
+    /// tenants elsewhere list their mobile prefixes
+    fn default() -> Self {
+        let prefixes = [
+            ("+", DestinationCategory::International),
+            ("011", DestinationCategory::International),
+            ("+1", DestinationCategory::National),
+            ("1", DestinationCategory::National),
+            ("+1900", DestinationCategory::Premium),
+            ("1900", DestinationCategory::Premium),
+            ("900", DestinationCategory::Premium),
+        ];
+        Self {
+            internal_max_digits: 5,
+            prefixes: prefixes
+                .into_iter()
+                .map(|(prefix, category)| (prefix.to_string(), category))
+                .collect(),
+            unmatched: DestinationCategory::Local,
+        }
+    }
+}
+
+impl CategoryPlan {
+    /// Category of a dialed destination (number or SIP URI)
+    pub fn categorize(&self, destination: &str) -> DestinationCategory {
+        let user = destination
+            .trim_start_matches("sips:")
+            .trim_start_matches("sip:")
+            .split(['@', ';'])
+            .next()
+            .unwrap_or_default();
+        let digits = user.strip_prefix('+').unwrap_or(user);
+        // Named users and short extensions never leave the PBX
+        if digits.is_empty() || !digits.chars().all(|c| c.is_ascii_digit()) {
+            return DestinationCategory::Internal;
+        }
+        if !user.starts_with('+') && digits.len() <= self.internal_max_digits {
+            return DestinationCategory::Internal;
+        }
+        self.prefixes
+            .iter()
+            .filter(|(prefix, _)| user.starts_with(prefix.as_str()))
+            .max_by_key(|(prefix, _)| prefix.len())
+            .map_or(self.unmatched, |(_, category)| *category)
+    }
+}
+
+/// Categories a class of service may call
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ServiceClass {
+    pub name: String,
+    #[serde(default)]
+    pub description: String,
+    pub allowed: Vec<DestinationCategory>,
+}
+
+impl ServiceClass {
+    pub fn new(name: &str, allowed: &[DestinationCategory]) -> Self {
+        Self {
+            name: name.to_string(),
+            description: String::new(),
+            allowed: allowed.to_vec(),
+        }
+    }
+
+    pub fn allows(&self, category: DestinationCategory) -> bool {
+        self.allowed.contains(&category)
+    }
+}
+
+/// Code a caller enters to place one call with the code's class
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AuthorizationCode {
+    /// Digits to enter, without the terminating `#`
+    pub code: String,
+    /// Class granted for the call
+    pub class: String,
+    /// Who or what the code is for; logged instead of the code
+    pub label: String,
+}
+
+/// Categories, classes and authorization codes of a tenant
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ClassOfServicePlan {
+    pub categories: CategoryPlan,
+    pub classes: Vec<ServiceClass>,
+    /// Class of users without an assignment
+    pub default_class: String,
+    pub authorization_codes: Vec<AuthorizationCode>,
+}
+
+impl Default for ClassOfServicePlan {
+    fn default() -> Self {
+        use DestinationCategory::*;
+        Self {
+            categories: CategoryPlan::default(),
+            classes: vec![
+                ServiceClass::new("internal", &[Internal]),
+                ServiceClass::new("local", &[Internal, Local]),
+                ServiceClass::new("national", &[Internal, Local, National, Mobile]),
+                ServiceClass::new("international", &[Internal, Local, National, Mobile, International]),
+                ServiceClass::new("unrestricted", &DestinationCategory::ALL),
+            ],
+            default_class: "national".to_string(),
+            authorization_codes: Vec::new(),
+        }
+    }
+}
+
+impl ClassOfServicePlan {
+    pub fn class(&self, name: &str) -> Option<&ServiceClass> {
+        self.classes.iter().find(|class| class.name == name)
+    }
+
+    pub fn validate(&self) -> Result<(), String> {
+        if self.class(&self.default_class).is_none() {
+            return Err(format!("default_class {} is not a class", self.default_class));
+        }
+        let mut codes = std::collections::HashSet::new();
+        for code in &self.authorization_codes {
+            if code.code.is_empty() || !code.code.chars().all(|c| c.is_ascii_digit()) {
+                return Err(format!("authorization code {} must be digits", code.label));
+            }
+            if !codes.insert(code.code.as_str()) {
+                return Err(format!("authorization code {} is not unique", code.label));
+            }
+            if self.class(&code.class).is_none() {
+                return Err(format!(
+                    "authorization code {} grants unknown class {}",
+                    code.label, code.class
+                ));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Class of service settings
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ClassOfServiceConfig {
+    /// Check calls of local users against their class
+    pub enabled: bool,
+    /// File assignments and overrides are snapshotted to
+    pub state_path: String,
+    /// Snapshot interval in seconds
+    pub persist_interval_secs: u64,
+    /// Audio file played to blocked callers; without, they get a 403
+    pub announcement: Option<String>,
+    /// Audio file asking for an authorization code
+    pub code_prompt: String,
+    /// Seconds a caller has to enter an authorization code
+    pub code_timeout_secs: u64,
+    /// Plan of tenants without their own
+    pub plan: ClassOfServicePlan,
+    /// Per-tenant plans, keyed by realm
+    pub tenants: HashMap<String, ClassOfServicePlan>,
+}
+
+impl Default for ClassOfServiceConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            state_path: "data/class_of_service.json".to_string(),
+            persist_interval_secs: 60,
+            announcement: Some("cos_not_authorized".to_string()),
+            code_prompt: "cos_enter_code".to_string(),
+            code_timeout_secs: 10,
+            plan: ClassOfServicePlan::default(),
+            tenants: HashMap::new(),
+        }
+    }
+}
+
+impl ClassOfServiceConfig {
+    pub fn validate(&self) -> Result<(), String> {
+        if self.persist_interval_secs == 0 {
+            return Err("persist_interval_secs must be at least 1".to_string());
+        }
+        if self.code_timeout_secs == 0 {
+            return Err("code_timeout_secs must be at least 1".to_string());
+        }
+        self.plan.validate()?;
+        for (realm, plan) in &self.tenants {
+            plan.validate().map_err(|e| format!("tenant {}: {}", realm, e))?;
+        }
+        Ok(())
+    }
+}
+
+/// Class of service errors
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+pub enum ClassOfServiceError {
+    #[error("Unknown class of service: {0}")]
+    UnknownClass(String),
+    #[error("Override must end in the future")]
+    OverrideExpired,
+    #[error("Invalid authorization code")]
+    InvalidCode,
+    #[error("Class {class} of the authorization code does not allow {category} calls")]
+    CodeNotSufficient { class: String, category: &'static str },
+}
+
+/// Temporary class of a user
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ClassOverride {
+    pub class: String,
+    pub until: DateTime<Utc>,
+    pub granted_by: String,
+}
+
+/// Entry of an authorization code, right or wrong
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CodeUse {
+    pub at: DateTime<Utc>,
+    pub call_id: String,
+    pub category: DestinationCategory,
+    /// Label of the code entered; `None` when it matched no code
+    pub label: Option<String>,
+    pub accepted: bool,
+}
+
+/// Per-user state (persisted)
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct UserClassState {
+    /// Assigned class; the tenant default without
+    pub class: Option<String>,
+    pub class_override: Option<ClassOverride>,
+    pub recent_code_uses: VecDeque<CodeUse>,
+}
+
+impl UserClassState {
+    fn expire(&mut self, now: DateTime<Utc>) {
+        if self.class_override.as_ref().is_some_and(|o| o.until <= now) {
+            self.class_override = None;
+        }
+    }
+}
+
+/// Class of service of a user
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct UserClassStatus {
+    pub username: String,
+    pub assigned_class: Option<String>,
+    pub default_class: String,
+    pub class_override: Option<ClassOverride>,
+    /// Class calls are checked against now
+    pub effective_class: String,
+    pub recent_code_uses: Vec<CodeUse>,
+}
+
+/// Call to check
+#[derive(Debug, Clone, Copy)]
+pub struct ClassCheck<'a> {
+    /// Tenant realm of the caller
+    pub realm: Option<&'a str>,
+    pub username: &'a str,
+    pub destination: &'a str,
+}
+
+/// Outcome of a class check
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ClassDecision {
+    pub category: DestinationCategory,
+    pub class: String,
+    pub allowed: bool,
+}
+
+/// Classes of service of users
+pub struct ClassOfServiceRegistry {
+    default_plan: ClassOfServicePlan,
+    tenant_plans: RwLock<HashMap<String, ClassOfServicePlan>>,
+    users: Mutex<HashMap<String, UserClassState>>,
+    announcement: Option<String>,
+    code_prompt: String,
+    code_timeout: std::time::Duration,
+}
+
+impl ClassOfServiceRegistry {
+    pub fn new(default_plan: ClassOfServicePlan) -> Self {
+        let defaults = ClassOfServiceConfig::default();
+        Self {
+            default_plan,
+            tenant_plans: RwLock::new(HashMap::new()),
+            users: Mutex::new(HashMap::new()),
+            announcement: defaults.announcement,
+            code_prompt: defaults.code_prompt,
+            code_timeout: std::time::Duration::from_secs(defaults.code_timeout_secs),
+        }
+    }
+
+    /// Registry with the plans and prompts of `config`
+    pub fn from_config(config: &ClassOfServiceConfig) -> Self {
+        let mut registry = Self::new(config.plan.clone());
+        registry.announcement = config.announcement.clone();
+        registry.code_prompt = config.code_prompt.clone();
+        registry.code_timeout = std::time::Duration::from_secs(config.code_timeout_secs);
+        for (realm, plan) in &config.tenants {
+            registry.set_tenant_plan(realm, plan.clone());
+        }
+        registry
+    }
+
+    /// Audio file played to blocked callers
+    pub fn announcement(&self) -> Option<&str> {
+        self.announcement.as_deref()
+    }
+
+    /// Audio file asking for an authorization code
+    pub fn code_prompt(&self) -> &str {
+        &self.code_prompt
+    }
+
+    /// Time a caller has to enter an authorization code
+    pub fn code_timeout(&self) -> std::time::Duration {
+        self.code_timeout
+    }
+
+    /// Override the plan of a tenant realm
+    pub fn set_tenant_plan(&self, realm: &str, plan: ClassOfServicePlan) {
+        self.tenant_plans
+            .write()
+            .unwrap()
+            .insert(realm.to_string(), plan);
+    }
+
+    /// Plan in effect for a realm
+    pub fn plan_for(&self, realm: Option<&str>) -> ClassOfServicePlan {
+        realm
+            .and_then(|r| self.tenant_plans.read().unwrap().get(r).cloned())
+            .unwrap_or_else(|| self.default_plan.clone())
+    }
+
+    /// Assign `class` to a user, or the tenant default with `None`
+    pub fn assign(
+        &self,
+        username: &str,
+        realm: Option<&str>,
+        class: Option<String>,
+    ) -> Result<(), ClassOfServiceError> {
+        if let Some(class) = &class {
+            if self.plan_for(realm).class(class).is_none() {
+                return Err(ClassOfServiceError::UnknownClass(class.clone()));
+            }
+        }
+        info!("Class of service of {} set to {:?}", username, class);
+        self.users
+            .lock()
+            .unwrap()
+            .entry(username.to_string())
+            .or_default()
+            .class = class;
+        Ok(())
+    }
+
+    /// Give a user `class` until `until`, in place of their own
+    pub fn set_override(
+        &self,
+        username: &str,
+        realm: Option<&str>,
+        class_override: ClassOverride,
+        now: DateTime<Utc>,
+    ) -> Result<(), ClassOfServiceError> {
+        if self.plan_for(realm).class(&class_override.class).is_none() {
+            return Err(ClassOfServiceError::UnknownClass(class_override.class));
+        }
+        if class_override.until <= now {
+            return Err(ClassOfServiceError::OverrideExpired);
+        }
+        info!(
+            "Class of service of {} overridden with {} until {} by {}",
+            username, class_override.class, class_override.until, class_override.granted_by
+        );
+        self.users
+            .lock()
+            .unwrap()
+            .entry(username.to_string())
+            .or_default()
+            .class_override = Some(class_override);
+        Ok(())
+    }
+
+    /// End a user's override early; false if there was none
+    pub fn clear_override(&self, username: &str) -> bool {
+        self.users
+            .lock()
+            .unwrap()
+            .get_mut(username)
+            .and_then(|state| state.class_override.take())
+            .is_some()
+    }
+
+    /// Class of a user at `now`
+    pub fn status(&self, username: &str, realm: Option<&str>, now: DateTime<Utc>) -> UserClassStatus {
+        let plan = self.plan_for(realm);
+        let mut users = self.users.lock().unwrap();
+        let state = users.entry(username.to_string()).or_default();
+        state.expire(now);
+        let effective_class = state
+            .class_override
+            .as_ref()
+            .map(|o| o.class.clone())
+            .or_else(|| state.class.clone())
+            .filter(|class| plan.class(class).is_some())
+            .unwrap_or_else(|| plan.default_class.clone());
+
+        UserClassStatus {
+            username: username.to_string(),
+            assigned_class: state.class.clone(),
+            default_class: plan.default_class.clone(),
+            class_override: state.class_override.clone(),
+            effective_class,
+            recent_code_uses: state.recent_code_uses.iter().cloned().collect(),
+        }
+    }
+
+    /// Check a call against the caller's class
+    pub fn check(&self, call: ClassCheck<'_>, now: DateTime<Utc>) -> ClassDecision {
+        let plan = self.plan_for(call.realm);
+        let category = plan.categories.categorize(call.destination);
+        let class = self.status(call.username, call.realm, now).effective_class;
+        let allowed = plan.class(&class).is_some_and(|c| c.allows(category));
+        counter!(
+            "cos_checks_total",
+            "category" => category.as_str(),
+            "allowed" => if allowed { "true" } else { "false" }
+        )
+        .increment(1);
+
+        ClassDecision {
+            category,
+            class,
+            allowed,
+        }
+    }
+
+    /// Whether callers of a realm can get past their class with a code
+    pub fn has_authorization_codes(&self, realm: Option<&str>) -> bool {
+        !self.plan_for(realm).authorization_codes.is_empty()
+    }
+
+    /// Validate a code entered for one call to `category`
+    ///
+    /// Returns the class the code grants. Every entry is logged with the
+    /// code's label, never the code itself.
+    pub fn authorize_code(
+        &self,
+        call: ClassCheck<'_>,
+        call_id: &str,
+        category: DestinationCategory,
+        code: &str,
+        now: DateTime<Utc>,
+    ) -> Result<String, ClassOfServiceError> {
+        let plan = self.plan_for(call.realm);
+        let entered = plan.authorization_codes.iter().find(|c| c.code == code);
+        let result = match entered {
+            None => Err(ClassOfServiceError::InvalidCode),
+            Some(entered) => match plan.class(&entered.class) {
+                Some(class) if class.allows(category) => Ok(class.name.clone()),
+                _ => Err(ClassOfServiceError::CodeNotSufficient {
+                    class: entered.class.clone(),
+                    category: category.as_str(),
+                }),
+            },
+        };
+
+        let label = entered.map(|c| c.label.clone());
+        match &result {
+            Ok(class) => info!(
+                "Call {} from {} to {} authorized by code {} (class {})",
+                call_id,
+                call.username,
+                call.destination,
+                label.as_deref().unwrap_or_default(),
+                class
+            ),
+            Err(e) => warn!(
+                "Authorization code for call {} from {} to {} refused: {}",
+                call_id, call.username, call.destination, e
+            ),
+        }
+        counter!(
+            "cos_authorization_codes_total",
+            "accepted" => if result.is_ok() { "true" } else { "false" }
+        )
+        .increment(1);
+
+        let mut users = self.users.lock().unwrap();
+        let uses = &mut users.entry(call.username.to_string()).or_default().recent_code_uses;
+        uses.push_back(CodeUse {
+            at: now,
+            call_id: call_id.to_string(),
+            category,
+            label,
+            accepted: result.is_ok(),
+        });
+        while uses.len() > RECENT_CODE_USES {
+            uses.pop_front();
+        }
+        result
+    }
+
+    /// Write assignments and overrides to `path`
+    pub fn save_to_file(&self, path: &Path) -> std::io::Result<()> {
+        let snapshot = self.users.lock().unwrap().clone();
+        let json = serde_json::to_vec(&snapshot)?;
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        // Write then rename so a crash never leaves a truncated snapshot
+        let tmp = path.with_extension("tmp");
+        std::fs::write(&tmp, json)?;
+        std::fs::rename(tmp, path)
+    }
+
+    /// Load state written by [`save_to_file`](Self::save_to_file)
+    pub fn load_from_file(&self, path: &Path) -> std::io::Result<()> {
+        let bytes = std::fs::read(path)?;
+        let snapshot: HashMap<String, UserClassState> = serde_json::from_slice(&bytes)?;
+        *self.users.lock().unwrap() = snapshot;
+        Ok(())
+    }
+
+    /// Snapshot the state to `path` every `interval`
+    pub fn spawn_persistence(
+        self: Arc<Self>,
+        path: PathBuf,
+        interval: std::time::Duration,
+    ) -> JoinHandle<()> {
+        tokio::spawn(async move {
+            let mut ticker = tokio::time::interval(interval);
+            ticker.tick().await;
+            loop {
+                ticker.tick().await;
+                if let Err(e) = self.save_to_file(&path) {
+                    error!("Failed to persist classes of service to {:?}: {}", path, e);
+                }
+            }
+        })
+    }
+}
+
+impl Default for ClassOfServiceRegistry {
+    fn default() -> Self {
+        Self::new(ClassOfServicePlan::default())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::Duration;
+
+    fn call(destination: &str) -> ClassCheck<'_> {
+        ClassCheck {
+            realm: None,
+            username: "alice",
+            destination,
+        }
+    }
+
+    #[test]
+    fn test_destinations_fall_in_the_longest_matching_prefix() {
+        let mut plan = CategoryPlan::default();
+        plan.prefixes.insert("44".to_string(), DestinationCategory::Local);
+        plan.prefixes.insert("447".to_string(), DestinationCategory::Mobile);
+
+        assert_eq!(plan.categorize("sip:1001@pbx.local"), DestinationCategory::Internal);
+        assert_eq!(plan.categorize("sip:alice@pbx.local"), DestinationCategory::Internal);
+        assert_eq!(plan.categorize("5551234"), DestinationCategory::Local);
+        assert_eq!(plan.categorize("sip:12125551234@pbx.local"), DestinationCategory::National);
+        assert_eq!(plan.categorize("+12125551234"), DestinationCategory::National);
+        assert_eq!(plan.categorize("+442071234567"), DestinationCategory::International);
+        assert_eq!(plan.categorize("01144207123456"), DestinationCategory::International);
+        assert_eq!(plan.categorize("19005551234"), DestinationCategory::Premium);
+        assert_eq!(plan.categorize("447700900123"), DestinationCategory::Mobile);
+        assert_eq!(plan.categorize("442071234567"), DestinationCategory::Local);
+    }
+
+    #[test]
+    fn test_override_expires() {
+        let registry = ClassOfServiceRegistry::default();
+        let now = Utc::now();
+        registry.assign("alice", None, Some("local".to_string())).unwrap();
+        assert!(!registry.check(call("12125551234"), now).allowed);
+
+        registry
+            .set_override(
+                "alice",
+                None,
+                ClassOverride {
+                    class: "international".to_string(),
+                    until: now + Duration::hours(1),
+                    granted_by: "admin".to_string(),
+                },
+                now,
+            )
+            .unwrap();
+        let decision = registry.check(call("+442071234567"), now);
+        assert_eq!(decision.class, "international");
+        assert!(decision.allowed);
+
+        let later = now + Duration::hours(2);
+        let decision = registry.check(call("+442071234567"), later);
+        assert_eq!(decision.class, "local");
+        assert!(!decision.allowed);
+        assert!(registry.status("alice", None, later).class_override.is_none());
+
+        assert_eq!(
+            registry.assign("alice", None, Some("gold".to_string())),
+            Err(ClassOfServiceError::UnknownClass("gold".to_string()))
+        );
+    }
+
+    #[test]
+    fn test_authorization_code_grants_one_call() {
+        let registry = ClassOfServiceRegistry::new(ClassOfServicePlan {
+            authorization_codes: vec![
+                AuthorizationCode {
+                    code: "4711".to_string(),
+                    class: "international".to_string(),
+                    label: "sales".to_string(),
+                },
+                AuthorizationCode {
+                    code: "1234".to_string(),
+                    class: "local".to_string(),
+                    label: "lobby".to_string(),
+                },
+            ],
+            ..Default::default()
+        });
+        let now = Utc::now();
+        let call = call("+442071234567");
+        let decision = registry.check(call, now);
+        assert!(!decision.allowed);
+        assert!(registry.has_authorization_codes(None));
+
+        assert_eq!(
+            registry.authorize_code(call, "call-1", decision.category, "9999", now),
+            Err(ClassOfServiceError::InvalidCode)
+        );
+        assert!(matches!(
+            registry.authorize_code(call, "call-1", decision.category, "1234", now),
+            Err(ClassOfServiceError::CodeNotSufficient { .. })
+        ));
+        assert_eq!(
+            registry.authorize_code(call, "call-1", decision.category, "4711", now),
+            Ok("international".to_string())
+        );
+        // Only for that call
+        assert!(!registry.check(call, now).allowed);
+
+        let uses = registry.status("alice", None, now).recent_code_uses;
+        assert_eq!(uses.len(), 3);
+        assert_eq!(uses[0].label, None);
+        assert_eq!(uses[2].label.as_deref(), Some("sales"));
+        assert!(uses[2].accepted);
+    }
+}
@@ -23,6 +23,121 @@ pub enum ParticipantState {
     Disconnected,
 }
 
+/// Floor state of a participant in lecture mode
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum FloorState {
+    /// Muted, may raise a hand
+    #[default]
+    Listener,
+    /// Muted, waiting for a moderator to grant the floor
+    HandRaised,
+    /// May talk
+    Speaker,
+}
+
+/// Lecture mode settings of a conference
+///
+/// Participants joining with the room PIN are listeners; those joining
+/// with `moderator_pin` moderate and grant or revoke the floor.
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq, Eq)]
+pub struct FloorControl {
+    pub moderator_pin: Option<String>,
+    /// Simultaneous speakers besides moderators; unlimited when None
+    pub max_speakers: Option<usize>,
+    /// Take the floor back from speakers silent for this many seconds
+    pub silence_release_secs: Option<u64>,
+}
+
+/// What a conference plays when someone joins or leaves
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum EntryExitCue {
+    #[default]
+    Silent,
+    /// A short rising (join) or falling (leave) tone
+    Tone,
+    /// "<recorded name> has joined" / "has left"
+    Name,
+}
+
+/// Entry and exit cues of a conference
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(default)]
+pub struct EntryExitSettings {
+    pub cue: EntryExitCue,
+    /// Rooms with more participants than this play tones instead of names,
+    /// so a crowd joining does not cause a storm of announcements
+    pub name_threshold: usize,
+    /// Gain of the conference audio while a cue plays (0.0 to 1.0)
+    pub duck_gain: f32,
+    /// Asks a joining participant to say their name
+    pub record_name_prompt: String,
+    /// Seconds a participant gets to say their name
+    pub name_max_secs: u64,
+    /// Played after the name of a participant who joined
+    pub joined_prompt: String,
+    /// Played after the name of a participant who left
+    pub left_prompt: String,
+}
+
+impl Default for EntryExitSettings {
+    fn default() -> Self {
+        Self {
+            cue: EntryExitCue::Silent,
+            name_threshold: 10,
+            duck_gain: 0.5,
+            record_name_prompt: "conf_record_name".to_string(),
+            name_max_secs: 3,
+            joined_prompt: "conf_has_joined".to_string(),
+            left_prompt: "conf_has_left".to_string(),
+        }
+    }
+}
+
+impl EntryExitSettings {
+    pub fn validate(&self) -> Result<(), String> {
+        if !(0.0..=1.0).contains(&self.duck_gain) {
+            return Err(format!("duck_gain must be between 0 and 1, got {}", self.duck_gain));
+        }
+        if self.cue == EntryExitCue::Name && self.name_max_secs == 0 {
+            return Err("name_max_secs must be at least 1".to_string());
+        }
+        Ok(())
+    }
+
+    /// Cue played in a room of `participants`
+    pub fn cue_for(&self, participants: usize) -> EntryExitCue {
+        match self.cue {
+            EntryExitCue::Name if participants > self.name_threshold => EntryExitCue::Tone,
+            cue => cue,
+        }
+    }
+}
+
+/// Why a participant's floor state changed
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum FloorChangeReason {
+    HandRaised,
+    HandLowered,
+    Granted,
+    Revoked,
+    /// The speaker was silent for longer than `silence_release_secs`
+    Silence,
+}
+
+/// Floor state change, published for moderator consoles
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct FloorEvent {
+    pub room_id: Uuid,
+    pub participant_id: Uuid,
+    pub name: String,
+    pub floor: FloorState,
+    pub reason: FloorChangeReason,
+    pub timestamp: DateTime<Utc>,
+}
+
 /// Conference participant
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Participant {
@@ -35,6 +150,12 @@ pub struct Participant {
     pub volume: f32,
     pub joined_at: DateTime<Utc>,
     pub left_at: Option<DateTime<Utc>>,
+    /// Floor state in lecture mode
+    #[serde(default)]
+    pub floor: FloorState,
+    /// Recording of the participant saying their name, for announcements
+    #[serde(default)]
+    pub name_recording: Option<String>,
 }
 
 impl Participant {
@@ -49,6 +170,8 @@ impl Participant {
             volume: 1.0,
             joined_at: Utc::now(),
             left_at: None,
+            floor: FloorState::Listener,
+            name_recording: None,
         }
     }
 
@@ -112,6 +235,12 @@ pub struct ConferenceRoom {
     pub ended_at: Option<DateTime<Utc>>,
     pub recording_enabled: bool,
     pub recording_file: Option<String>,
+    /// Lecture mode; None for an open conference where everyone talks
+    #[serde(default)]
+    pub floor_control: Option<FloorControl>,
+    /// What is played when someone joins or leaves
+    #[serde(default)]
+    pub entry_exit: EntryExitSettings,
 }
 
 impl ConferenceRoom {
@@ -129,6 +258,8 @@ impl ConferenceRoom {
             ended_at: None,
             recording_enabled: false,
             recording_file: None,
+            floor_control: None,
+            entry_exit: EntryExitSettings::default(),
         }
     }
 
@@ -140,6 +271,119 @@ impl ConferenceRoom {
         }
     }
 
+    /// Role of a participant joining with `pin`
+    ///
+    /// In lecture mode the PIN decides: the moderator PIN makes a
+    /// moderator, the room PIN a listener. Otherwise `requested` is kept.
+    pub fn role_for_pin(
+        &self,
+        pin: Option<&str>,
+        requested: ParticipantRole,
+    ) -> Result<ParticipantRole, String> {
+        let moderator_pin = self
+            .floor_control
+            .as_ref()
+            .and_then(|control| control.moderator_pin.as_deref());
+        if let (Some(pin), Some(moderator_pin)) = (pin, moderator_pin) {
+            if pin == moderator_pin {
+                return Ok(ParticipantRole::Moderator);
+            }
+        }
+
+        match pin {
+            Some(pin) if !self.verify_pin(pin) => return Err("Invalid PIN".to_string()),
+            None if self.pin.is_some() => return Err("PIN required".to_string()),
+            _ => {}
+        }
+
+        if self.floor_control.is_some() {
+            Ok(ParticipantRole::Listener)
+        } else {
+            Ok(requested)
+        }
+    }
+
+    /// Switch to lecture mode: everyone but moderators is muted and
+    /// becomes a listener
+    pub fn enable_floor_control(&mut self, control: FloorControl) {
+        self.floor_control = Some(control);
+        for participant in self.participants.values_mut() {
+            if participant.is_moderator() {
+                participant.floor = FloorState::Speaker;
+            } else {
+                participant.floor = FloorState::Listener;
+                participant.mute();
+            }
+        }
+    }
+
+    /// Whether the conference runs in lecture mode
+    pub fn is_lecture(&self) -> bool {
+        self.floor_control.is_some()
+    }
+
+    /// Number of participants other than moderators holding the floor
+    pub fn speaker_count(&self) -> usize {
+        self.participants
+            .values()
+            .filter(|p| !p.is_moderator() && p.floor == FloorState::Speaker)
+            .count()
+    }
+
+    /// Raise or lower a listener's hand; returns the new state
+    pub fn toggle_hand(&mut self, participant_id: Uuid) -> Result<FloorState, String> {
+        if !self.is_lecture() {
+            return Err("Conference is not in lecture mode".to_string());
+        }
+        let participant = self
+            .get_participant_mut(participant_id)
+            .ok_or_else(|| "Participant not found".to_string())?;
+        participant.floor = match participant.floor {
+            FloorState::Listener => FloorState::HandRaised,
+            FloorState::HandRaised => FloorState::Listener,
+            FloorState::Speaker => return Err("Participant already has the floor".to_string()),
+        };
+        Ok(participant.floor)
+    }
+
+    /// Give a participant the floor and unmute them
+    pub fn grant_floor(&mut self, participant_id: Uuid) -> Result<(), String> {
+        let max_speakers = self
+            .floor_control
+            .as_ref()
+            .ok_or_else(|| "Conference is not in lecture mode".to_string())?
+            .max_speakers;
+        let speakers = self.speaker_count();
+        let participant = self
+            .get_participant_mut(participant_id)
+            .ok_or_else(|| "Participant not found".to_string())?;
+        if participant.floor == FloorState::Speaker {
+            return Ok(());
+        }
+        if max_speakers.is_some_and(|max| speakers >= max) {
+            return Err("Speaker limit reached".to_string());
+        }
+        participant.floor = FloorState::Speaker;
+        participant.unmute();
+        Ok(())
+    }
+
+    /// Take the floor back from a participant and mute them
+    pub fn revoke_floor(&mut self, participant_id: Uuid) -> Result<(), String> {
+        if !self.is_lecture() {
+            return Err("Conference is not in lecture mode".to_string());
+        }
+        let participant = self
+            .get_participant_mut(participant_id)
+            .ok_or_else(|| "Participant not found".to_string())?;
+        if participant.is_moderator() {
+            return Err("Moderators always have the floor".to_string());
+        }
+        participant.floor = FloorState::Listener;
+        participant.mute();
+        Ok(())
+    }
+
     /// Start conference
     pub fn start(&mut self) -> Result<(), String> {
         if self.state == ConferenceState::Active {
@@ -156,7 +400,9 @@ impl ConferenceRoom {
     }
 
     /// Add participant to conference
-    pub fn add_participant(&mut self, participant: Participant) -> Result<(), String> {
+    ///
+    /// In lecture mode participants other than moderators join muted.
+    pub fn add_participant(&mut self, mut participant: Participant) -> Result<(), String> {
         if self.state == ConferenceState::Locked {
             return Err("Conference is locked".to_string());
         }
@@ -172,6 +418,15 @@ impl ConferenceRoom {
         let participant_id = participant.id;
         let is_moderator = participant.is_moderator();
 
+        if self.is_lecture() {
+            if is_moderator {
+                participant.floor = FloorState::Speaker;
+            } else {
+                participant.floor = FloorState::Listener;
+                participant.mute();
+            }
+        }
+
         self.participants.insert(participant_id, participant);
 
         // If this is a moderator and conference is waiting, start it
@@ -521,6 +776,71 @@ mod tests {
         assert!(!room.recording_enabled);
     }
 
+    #[test]
+    fn test_role_decided_by_pin_in_lecture_mode() {
+        let mut room = ConferenceRoom::new("All hands".to_string(), Some("1111".to_string()), 500);
+        assert_eq!(
+            room.role_for_pin(Some("1111"), ParticipantRole::Presenter).unwrap(),
+            ParticipantRole::Presenter
+        );
+
+        room.enable_floor_control(FloorControl {
+            moderator_pin: Some("9999".to_string()),
+            ..Default::default()
+        });
+        assert_eq!(
+            room.role_for_pin(Some("9999"), ParticipantRole::Attendee).unwrap(),
+            ParticipantRole::Moderator
+        );
+        assert_eq!(
+            room.role_for_pin(Some("1111"), ParticipantRole::Moderator).unwrap(),
+            ParticipantRole::Listener
+        );
+        assert!(room.role_for_pin(Some("2222"), ParticipantRole::Attendee).is_err());
+        assert!(room.role_for_pin(None, ParticipantRole::Attendee).is_err());
+    }
+
+    #[test]
+    fn test_floor_control() {
+        let mut room = ConferenceRoom::new("All hands".to_string(), None, 500);
+        room.enable_floor_control(FloorControl {
+            max_speakers: Some(1),
+            ..Default::default()
+        });
+
+        let moderator = Participant::new("Host".to_string(), "call-mod".to_string(), ParticipantRole::Moderator);
+        let p1 = Participant::new("User1".to_string(), "call-1".to_string(), ParticipantRole::Listener);
+        let p2 = Participant::new("User2".to_string(), "call-2".to_string(), ParticipantRole::Listener);
+        let (moderator_id, p1_id, p2_id) = (moderator.id, p1.id, p2.id);
+        room.add_participant(moderator).unwrap();
+        room.add_participant(p1).unwrap();
+        room.add_participant(p2).unwrap();
+
+        // Listeners join muted, moderators talk
+        assert!(room.get_participant(p1_id).unwrap().is_muted);
+        assert!(!room.get_participant(moderator_id).unwrap().is_muted);
+        assert_eq!(room.get_participant(moderator_id).unwrap().floor, FloorState::Speaker);
+
+        assert_eq!(room.toggle_hand(p1_id).unwrap(), FloorState::HandRaised);
+        assert_eq!(room.toggle_hand(p2_id).unwrap(), FloorState::HandRaised);
+        assert_eq!(room.toggle_hand(p2_id).unwrap(), FloorState::Listener);
+
+        room.grant_floor(p1_id).unwrap();
+        let speaker = room.get_participant(p1_id).unwrap();
+        assert_eq!(speaker.floor, FloorState::Speaker);
+        assert!(!speaker.is_muted);
+        assert!(room.toggle_hand(p1_id).is_err());
+
+        // One speaker at a time
+        assert!(room.grant_floor(p2_id).unwrap_err().contains("limit"));
+
+        room.revoke_floor(p1_id).unwrap();
+        assert!(room.get_participant(p1_id).unwrap().is_muted);
+        assert!(room.revoke_floor(moderator_id).is_err());
+        room.grant_floor(p2_id).unwrap();
+        assert_eq!(room.speaker_count(), 1);
+    }
+
     #[test]
     fn test_participant_toggle_mute() {
         let mut participant = Participant::new(
@@ -537,4 +857,23 @@ mod tests {
         participant.toggle_mute();
         assert!(!participant.is_muted);
     }
+
+    #[test]
+    fn test_entry_exit_names_fall_back_to_tones() {
+        let settings = EntryExitSettings {
+            cue: EntryExitCue::Name,
+            name_threshold: 3,
+            ..Default::default()
+        };
+
+        assert_eq!(settings.cue_for(3), EntryExitCue::Name);
+        assert_eq!(settings.cue_for(4), EntryExitCue::Tone);
+        assert_eq!(EntryExitSettings::default().cue_for(1), EntryExitCue::Silent);
+
+        let invalid = EntryExitSettings {
+            duck_gain: 1.5,
+            ..Default::default()
+        };
+        assert!(invalid.validate().is_err());
+    }
 }
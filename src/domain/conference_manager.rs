@@ -3,15 +3,34 @@
 /// Manages active conferences, integrates with audio mixing, and coordinates
 /// between SIP calls and conference participants.
 
-use crate::domain::conference::{ConferenceRoom, Participant, ParticipantRole, ConferenceState};
-use crate::infrastructure::media::{AudioMixer, AudioFrame};
+use crate::domain::audio::{AudioLibrary, WavFile};
+use crate::domain::call_announcer::{AnnouncementRequest, AnnouncementType};
+use crate::domain::conference::{
+    ConferenceRoom, ConferenceState, EntryExitCue, EntryExitSettings, FloorChangeReason,
+    FloorControl, FloorEvent, FloorState, Participant, ParticipantRole,
+};
+use crate::domain::voicemail::VoicemailRepository;
+use crate::infrastructure::ivr::{DtmfDigit, DtmfDispatcher};
+use crate::infrastructure::media::{
+    AudioMixer, AudioFrame, BandwidthEstimate, BandwidthMonitor, ToneGenerator,
+};
+use chrono::Utc;
 use std::collections::HashMap;
 use std::sync::Arc;
-use tokio::sync::RwLock;
+use std::time::{Duration, Instant};
+use tokio::sync::{broadcast, RwLock};
+use tokio::task::JoinHandle;
 use tracing::{debug, info, warn};
 use uuid::Uuid;
 
+/// Sample rate of conference audio
+const SAMPLE_RATE: u32 = 8000;
+
+/// Length of each note of the entry and exit tones (100 ms)
+const CUE_NOTE_SAMPLES: usize = 800;
+
 /// Conference manager for coordinating multi-party calls
+#[derive(Clone)]
 pub struct ConferenceManager {
     /// Active conference rooms (room_id -> room)
     rooms: Arc<RwLock<HashMap<Uuid, ConferenceRoom>>>,
@@ -19,18 +38,68 @@ pub struct ConferenceManager {
     mixers: Arc<RwLock<HashMap<Uuid, Arc<AudioMixer>>>>,
     /// Call ID to participant mapping (call_id -> (room_id, participant_id))
     call_participants: Arc<RwLock<HashMap<String, (Uuid, Uuid)>>>,
+    /// Floor changes in lecture mode conferences
+    floor_events: broadcast::Sender<FloorEvent>,
+    /// DTMF of participant calls, for raising hands with *5
+    dtmf: Option<Arc<DtmfDispatcher>>,
+    /// Bandwidth estimates of participants' WebRTC legs
+    bandwidth: Option<Arc<BandwidthMonitor>>,
+    /// Prompts of name announcements
+    audio_library: Option<Arc<AudioLibrary>>,
+    /// Stored name greetings of internal participants
+    voicemail: Option<Arc<dyn VoicemailRepository>>,
 }
 
 impl ConferenceManager {
     /// Create new conference manager
     pub fn new() -> Self {
+        let (floor_events, _) = broadcast::channel(256);
         Self {
             rooms: Arc::new(RwLock::new(HashMap::new())),
             mixers: Arc::new(RwLock::new(HashMap::new())),
             call_participants: Arc::new(RwLock::new(HashMap::new())),
+            floor_events,
+            dtmf: None,
+            bandwidth: None,
+            audio_library: None,
+            voicemail: None,
         }
     }
 
+    /// Let participants raise their hand by pressing *5
+    pub fn with_dtmf(mut self, dtmf: Arc<DtmfDispatcher>) -> Self {
+        self.dtmf = Some(dtmf);
+        self
+    }
+
+    /// Report the bandwidth estimates of participants' legs from `monitor`
+    pub fn with_bandwidth(mut self, monitor: Arc<BandwidthMonitor>) -> Self {
+        self.bandwidth = Some(monitor);
+        self
+    }
+
+    /// Take the prompts of name announcements from `library`
+    pub fn with_audio_library(mut self, library: Arc<AudioLibrary>) -> Self {
+        self.audio_library = Some(library);
+        self
+    }
+
+    /// Announce internal participants with their mailbox's recorded name
+    pub fn with_voicemail(mut self, voicemail: Arc<dyn VoicemailRepository>) -> Self {
+        self.voicemail = Some(voicemail);
+        self
+    }
+
+    /// Bandwidth estimates of a participant's leg, when it is estimated
+    pub fn bandwidth_estimate(&self, call_id: &str) -> Option<BandwidthEstimate> {
+        self.bandwidth.as_ref()?.estimate(call_id)
+    }
+
+    /// Receive floor changes (hands raised, floor granted or released)
+    pub fn subscribe_floor_events(&self) -> broadcast::Receiver<FloorEvent> {
+        self.floor_events.subscribe()
+    }
+
     /// Create a new conference room
     pub async fn create_room(
         &self,
@@ -42,7 +111,7 @@ impl ConferenceManager {
         let room_id = room.id;
 
         // Create audio mixer for this conference (8kHz telephony sample rate, mono)
-        let mixer = Arc::new(AudioMixer::new(8000, 1));
+        let mixer = Arc::new(AudioMixer::new(SAMPLE_RATE, 1));
 
         let mut rooms = self.rooms.write().await;
         let mut mixers = self.mixers.write().await;
@@ -71,42 +140,70 @@ impl ConferenceManager {
         name: String,
         role: ParticipantRole,
         pin: Option<String>,
+    ) -> Result<Uuid, String> {
+        self.join_conference_named(room_id, call_id, name, role, pin, None)
+            .await
+    }
+
+    /// Join conference, announced with `name_recording` if the room
+    /// announces names
+    pub async fn join_conference_named(
+        &self,
+        room_id: Uuid,
+        call_id: String,
+        name: String,
+        role: ParticipantRole,
+        pin: Option<String>,
+        name_recording: Option<String>,
     ) -> Result<Uuid, String> {
         let mut rooms = self.rooms.write().await;
         let room = rooms
             .get_mut(&room_id)
             .ok_or_else(|| "Conference room not found".to_string())?;
 
-        // Verify PIN if required
-        if let Some(pin_value) = pin {
-            if !room.verify_pin(&pin_value) {
-                return Err("Invalid PIN".to_string());
-            }
-        } else if room.pin.is_some() {
-            return Err("PIN required".to_string());
-        }
+        // Verify PIN; in lecture mode it also decides the role
+        let role = room.role_for_pin(pin.as_deref(), role)?;
 
         // Create participant
-        let participant = Participant::new(name, call_id.clone(), role);
+        let mut participant = Participant::new(name, call_id.clone(), role);
+        participant.name_recording = name_recording.clone();
         let participant_id = participant.id;
 
         // Add to conference
         room.add_participant(participant)?;
+        let muted = room
+            .get_participant(participant_id)
+            .is_some_and(|p| p.is_muted);
+        let cue = room.entry_exit.clone();
+        let participants = room.participant_count();
 
         // Add to audio mixer
         let mixers = self.mixers.read().await;
-        if let Some(mixer) = mixers.get(&room_id) {
+        let mixer = mixers.get(&room_id).cloned();
+        drop(mixers);
+        if let Some(mixer) = &mixer {
             mixer.add_stream(participant_id).await;
+            if muted {
+                mixer.mute_participant(participant_id).await?;
+            }
         }
 
         // Track call -> participant mapping
         let mut call_participants = self.call_participants.write().await;
         call_participants.insert(call_id.clone(), (room_id, participant_id));
+        self.watch_raise_hand_keys(call_id.clone());
 
         info!(
             "Participant {} joined conference {} (call: {})",
             participant_id, room_id, call_id
         );
+        drop(call_participants);
+        drop(rooms);
+
+        if let Some(mixer) = mixer {
+            self.play_cue(&mixer, &cue, participants, name_recording.as_deref(), true)
+                .await;
+        }
         Ok(participant_id)
     }
 
@@ -122,18 +219,31 @@ impl ConferenceManager {
 
         // Remove from conference room
         let mut rooms = self.rooms.write().await;
+        let mut cue = None;
+        let mut ended = false;
         if let Some(room) = rooms.get_mut(&room_id) {
+            let name_recording = room
+                .get_participant(participant_id)
+                .and_then(|p| p.name_recording.clone());
+            let participants = room.participant_count();
             room.remove_participant(participant_id)?;
 
-            // If room is ended, clean up
             if room.state == ConferenceState::Ended {
-                self.cleanup_room(room_id).await?;
+                ended = true;
+            } else {
+                cue = Some((room.entry_exit.clone(), participants, name_recording));
             }
         }
+        drop(rooms);
+
+        // If room is ended, clean up
+        if ended {
+            self.cleanup_room(room_id).await?;
+        }
 
         // Remove from audio mixer
-        let mixers = self.mixers.read().await;
-        if let Some(mixer) = mixers.get(&room_id) {
+        let mixer = self.mixers.read().await.get(&room_id).cloned();
+        if let Some(mixer) = &mixer {
             mixer.remove_stream(participant_id).await;
         }
 
@@ -141,9 +251,106 @@ impl ConferenceManager {
             "Participant {} left conference {} (call: {})",
             participant_id, room_id, call_id
         );
+
+        if let (Some(mixer), Some((cue, participants, name_recording))) = (mixer, cue) {
+            self.play_cue(&mixer, &cue, participants, name_recording.as_deref(), false)
+                .await;
+        }
         Ok(())
     }
 
+    /// Set what a room plays when someone joins or leaves
+    pub async fn set_entry_exit(
+        &self,
+        room_id: Uuid,
+        settings: EntryExitSettings,
+    ) -> Result<(), String> {
+        settings.validate()?;
+        let mut rooms = self.rooms.write().await;
+        let room = rooms
+            .get_mut(&room_id)
+            .ok_or_else(|| "Conference room not found".to_string())?;
+        room.entry_exit = settings;
+        Ok(())
+    }
+
+    /// Prompt asking a caller about to join to say their name, recorded
+    /// for the join announcement; `None` when the room would not announce it
+    pub async fn record_name_prompt(
+        &self,
+        room_id: Uuid,
+        call_id: &str,
+    ) -> Result<Option<AnnouncementRequest>, String> {
+        let rooms = self.rooms.read().await;
+        let room = rooms
+            .get(&room_id)
+            .ok_or_else(|| "Conference room not found".to_string())?;
+        if room.entry_exit.cue_for(room.participant_count() + 1) != EntryExitCue::Name {
+            return Ok(None);
+        }
+        Ok(Some(
+            AnnouncementRequest::new(call_id.to_string(), AnnouncementType::Custom)
+                .add_audio(&room.entry_exit.record_name_prompt),
+        ))
+    }
+
+    /// Recorded name of an internal participant's mailbox
+    pub async fn stored_name_recording(&self, mailbox_id: &str) -> Option<String> {
+        match self.voicemail.as_ref()?.get_mailbox(mailbox_id).await {
+            Ok(mailbox) => mailbox?.name_greeting_file,
+            Err(e) => {
+                warn!("Failed to load mailbox {}: {}", mailbox_id, e);
+                None
+            }
+        }
+    }
+
+    /// Play the cue for a participant who joined or left into the room
+    async fn play_cue(
+        &self,
+        mixer: &AudioMixer,
+        settings: &EntryExitSettings,
+        participants: usize,
+        name_recording: Option<&str>,
+        joined: bool,
+    ) {
+        let samples = match settings.cue_for(participants) {
+            EntryExitCue::Silent => return,
+            EntryExitCue::Name => match self.name_announcement(settings, name_recording, joined) {
+                Some(samples) => samples,
+                None => cue_tone(joined).await,
+            },
+            EntryExitCue::Tone => cue_tone(joined).await,
+        };
+        mixer.announce(Arc::new(samples), settings.duck_gain).await;
+    }
+
+    /// "<name> has joined" / "has left"; `None` without a recorded name or
+    /// the prompt
+    fn name_announcement(
+        &self,
+        settings: &EntryExitSettings,
+        name_recording: Option<&str>,
+        joined: bool,
+    ) -> Option<Vec<i16>> {
+        let name = match WavFile::from_file_converted(name_recording?, SAMPLE_RATE) {
+            Ok(name) => name.samples_i16(),
+            Err(e) => {
+                warn!("Failed to load name recording {:?}: {}", name_recording, e);
+                return None;
+            }
+        };
+        let prompt = if joined { &settings.joined_prompt } else { &settings.left_prompt };
+        let prompt = match self.audio_library.as_ref()?.pcm(None, prompt) {
+            Ok(prompt) => prompt,
+            Err(e) => {
+                warn!("Failed to load conference prompt {}: {}", prompt, e);
+                return None;
+            }
+        };
+        Some(name.into_iter().chain(prompt.iter().copied()).collect())
+    }
+
     /// Mute participant in conference
     pub async fn mute_participant(&self, call_id: &str) -> Result<(), String> {
         let call_participants = self.call_participants.read().await;
@@ -207,6 +414,194 @@ impl ConferenceManager {
         Ok(())
     }
 
+    /// Switch a conference to lecture mode
+    pub async fn enable_floor_control(
+        &self,
+        room_id: Uuid,
+        control: FloorControl,
+    ) -> Result<(), String> {
+        let mut rooms = self.rooms.write().await;
+        let room = rooms
+            .get_mut(&room_id)
+            .ok_or_else(|| "Conference room not found".to_string())?;
+        room.enable_floor_control(control);
+
+        let mixers = self.mixers.read().await;
+        if let Some(mixer) = mixers.get(&room_id) {
+            for participant in room.participants.values() {
+                if participant.is_muted {
+                    mixer.mute_participant(participant.id).await?;
+                }
+            }
+        }
+
+        info!("Conference {} switched to lecture mode", room_id);
+        Ok(())
+    }
+
+    /// Raise or lower the hand of the participant on `call_id`
+    pub async fn raise_hand(&self, call_id: &str) -> Result<FloorState, String> {
+        let (room_id, participant_id) = self
+            .call_participants
+            .read()
+            .await
+            .get(call_id)
+            .copied()
+            .ok_or_else(|| "Call not in conference".to_string())?;
+
+        let mut rooms = self.rooms.write().await;
+        let room = rooms
+            .get_mut(&room_id)
+            .ok_or_else(|| "Conference room not found".to_string())?;
+        let floor = room.toggle_hand(participant_id)?;
+
+        let reason = if floor == FloorState::HandRaised {
+            FloorChangeReason::HandRaised
+        } else {
+            FloorChangeReason::HandLowered
+        };
+        self.publish_floor(room, participant_id, reason);
+        Ok(floor)
+    }
+
+    /// Give a participant the floor, unmuting them in the mix
+    pub async fn grant_floor(&self, room_id: Uuid, participant_id: Uuid) -> Result<(), String> {
+        let mut rooms = self.rooms.write().await;
+        let room = rooms
+            .get_mut(&room_id)
+            .ok_or_else(|| "Conference room not found".to_string())?;
+        room.grant_floor(participant_id)?;
+
+        let mixers = self.mixers.read().await;
+        if let Some(mixer) = mixers.get(&room_id) {
+            mixer.unmute_participant(participant_id).await?;
+            mixer.reset_voice_activity(participant_id).await;
+        }
+
+        info!("Participant {} has the floor in conference {}", participant_id, room_id);
+        self.publish_floor(room, participant_id, FloorChangeReason::Granted);
+        Ok(())
+    }
+
+    /// Take the floor back from a participant, muting them in the mix
+    pub async fn revoke_floor(&self, room_id: Uuid, participant_id: Uuid) -> Result<(), String> {
+        let mut rooms = self.rooms.write().await;
+        let room = rooms
+            .get_mut(&room_id)
+            .ok_or_else(|| "Conference room not found".to_string())?;
+        self.take_floor(room, participant_id, FloorChangeReason::Revoked)
+            .await
+    }
+
+    /// Take the floor back from speakers who have been silent for longer
+    /// than their conference allows; returns the participants released
+    pub async fn release_silent_speakers(&self, now: Instant) -> Vec<Uuid> {
+        let mut released = Vec::new();
+        let mut rooms = self.rooms.write().await;
+        for room in rooms.values_mut() {
+            let Some(secs) = room
+                .floor_control
+                .as_ref()
+                .and_then(|control| control.silence_release_secs)
+            else {
+                continue;
+            };
+            let speakers: Vec<Uuid> = room
+                .participants
+                .values()
+                .filter(|p| !p.is_moderator() && p.floor == FloorState::Speaker)
+                .map(|p| p.id)
+                .collect();
+
+            for participant_id in speakers {
+                let last_voice = match self.mixers.read().await.get(&room.id) {
+                    Some(mixer) => mixer.last_voice(participant_id).await,
+                    None => None,
+                };
+                let silent = last_voice.is_none_or(|last| {
+                    now.saturating_duration_since(last) >= Duration::from_secs(secs)
+                });
+                if !silent {
+                    continue;
+                }
+                match self
+                    .take_floor(room, participant_id, FloorChangeReason::Silence)
+                    .await
+                {
+                    Ok(()) => released.push(participant_id),
+                    Err(e) => warn!("Failed to release floor of {}: {}", participant_id, e),
+                }
+            }
+        }
+        released
+    }
+
+    async fn take_floor(
+        &self,
+        room: &mut ConferenceRoom,
+        participant_id: Uuid,
+        reason: FloorChangeReason,
+    ) -> Result<(), String> {
+        room.revoke_floor(participant_id)?;
+
+        let mixers = self.mixers.read().await;
+        if let Some(mixer) = mixers.get(&room.id) {
+            mixer.mute_participant(participant_id).await?;
+        }
+
+        info!(
+            "Participant {} lost the floor in conference {} ({:?})",
+            participant_id, room.id, reason
+        );
+        self.publish_floor(room, participant_id, reason);
+        Ok(())
+    }
+
+    fn publish_floor(&self, room: &ConferenceRoom, participant_id: Uuid, reason: FloorChangeReason) {
+        let Some(participant) = room.get_participant(participant_id) else {
+            return;
+        };
+        // Ignore send errors (no receivers)
+        let _ = self.floor_events.send(FloorEvent {
+            room_id: room.id,
+            participant_id,
+            name: participant.name.clone(),
+            floor: participant.floor,
+            reason,
+            timestamp: Utc::now(),
+        });
+    }
+
+    /// Toggle the hand of the participant on `call_id` whenever they
+    /// press *5, until the call leaves the conference
+    fn watch_raise_hand_keys(&self, call_id: String) {
+        let Some(dtmf) = &self.dtmf else {
+            return;
+        };
+        let mut rx = dtmf.subscribe(&call_id);
+        let manager = self.clone();
+        tokio::spawn(async move {
+            let mut star = false;
+            loop {
+                match rx.recv().await {
+                    Ok(event) => {
+                        if !manager.is_in_conference(&call_id).await {
+                            break;
+                        }
+                        if star && event.digit == DtmfDigit::Five {
+                            if let Err(e) = manager.raise_hand(&call_id).await {
+                                debug!("Ignoring *5 from {}: {}", call_id, e);
+                            }
+                        }
+                        star = event.digit == DtmfDigit::Star;
+                    }
+                    Err(broadcast::error::RecvError::Lagged(_)) => star = false,
+                    Err(broadcast::error::RecvError::Closed) => break,
+                }
+            }
+        });
+    }
+
     /// Mix audio for a specific participant (excludes their own audio)
     pub async fn mix_audio_for_participant(
         &self,
@@ -335,6 +730,31 @@ impl Default for ConferenceManager {
     }
 }
 
+/// Rising two-note tone for a join, falling for a leave
+async fn cue_tone(joined: bool) -> Vec<i16> {
+    let notes = if joined { [440.0, 660.0] } else { [660.0, 440.0] };
+    let mut samples = Vec::with_capacity(notes.len() * CUE_NOTE_SAMPLES);
+    for frequency in notes {
+        let tone = ToneGenerator::new(frequency, SAMPLE_RATE, 0.2);
+        samples.extend(tone.generate_samples(CUE_NOTE_SAMPLES).await);
+    }
+    samples
+}
+
+/// Check every `interval` for speakers to release after silence
+pub fn spawn_silence_release(
+    manager: Arc<ConferenceManager>,
+    interval: Duration,
+) -> JoinHandle<()> {
+    tokio::spawn(async move {
+        let mut ticker = tokio::time::interval(interval);
+        loop {
+            ticker.tick().await;
+            manager.release_silent_speakers(Instant::now()).await;
+        }
+    })
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -471,6 +891,156 @@ mod tests {
         assert!(result.is_ok());
     }
 
+    #[tokio::test]
+    async fn test_lecture_floor_control() {
+        let dtmf = Arc::new(DtmfDispatcher::new());
+        let manager = ConferenceManager::new().with_dtmf(dtmf.clone());
+        let mut events = manager.subscribe_floor_events();
+        let room_id = manager
+            .create_room("All hands".to_string(), Some("1111".to_string()), 500)
+            .await
+            .unwrap();
+        manager
+            .enable_floor_control(
+                room_id,
+                FloorControl {
+                    moderator_pin: Some("9999".to_string()),
+                    max_speakers: Some(2),
+                    silence_release_secs: Some(30),
+                },
+            )
+            .await
+            .unwrap();
+
+        let host = manager
+            .join_conference(
+                room_id,
+                "call-host".to_string(),
+                "Host".to_string(),
+                ParticipantRole::Attendee,
+                Some("9999".to_string()),
+            )
+            .await
+            .unwrap();
+        // The room PIN admits listeners, whatever role is asked for
+        let listener = manager
+            .join_conference(
+                room_id,
+                "call-1".to_string(),
+                "Alice".to_string(),
+                ParticipantRole::Moderator,
+                Some("1111".to_string()),
+            )
+            .await
+            .unwrap();
+
+        let room = manager.get_room(room_id).await.unwrap();
+        assert!(room.is_moderator(host));
+        let alice = room.get_participant(listener).unwrap();
+        assert_eq!(alice.role, ParticipantRole::Listener);
+        assert!(alice.is_muted);
+
+        // Listener presses *5
+        for digit in [DtmfDigit::Star, DtmfDigit::Five] {
+            dtmf.publish(
+                "call-1",
+                crate::infrastructure::ivr::DtmfEvent::new(digit, Duration::from_millis(100)),
+            );
+        }
+        let event = tokio::time::timeout(Duration::from_secs(1), events.recv())
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(event.participant_id, listener);
+        assert_eq!(event.reason, FloorChangeReason::HandRaised);
+        assert_eq!(event.floor, FloorState::HandRaised);
+
+        // Moderator grants the floor
+        manager.grant_floor(room_id, listener).await.unwrap();
+        let event = events.recv().await.unwrap();
+        assert_eq!(event.reason, FloorChangeReason::Granted);
+        let alice = manager.get_room(room_id).await.unwrap().participants[&listener].clone();
+        assert_eq!(alice.floor, FloorState::Speaker);
+        assert!(!alice.is_muted);
+
+        // Still talking within the silence window
+        assert!(manager
+            .release_silent_speakers(Instant::now() + Duration::from_secs(10))
+            .await
+            .is_empty());
+
+        // Silent for longer than allowed
+        let released = manager
+            .release_silent_speakers(Instant::now() + Duration::from_secs(31))
+            .await;
+        assert_eq!(released, vec![listener]);
+        let event = events.recv().await.unwrap();
+        assert_eq!(event.reason, FloorChangeReason::Silence);
+        assert_eq!(event.floor, FloorState::Listener);
+        let alice = manager.get_room(room_id).await.unwrap().participants[&listener].clone();
+        assert!(alice.is_muted);
+
+        // Moderators are never released
+        let room = manager.get_room(room_id).await.unwrap();
+        assert_eq!(room.participants[&host].floor, FloorState::Speaker);
+    }
+
+    #[tokio::test]
+    async fn test_entry_exit_cues() {
+        let manager = ConferenceManager::new();
+        let room_id = manager
+            .create_room("Standup".to_string(), None, 10)
+            .await
+            .unwrap();
+        let mixer = manager.mixers.read().await[&room_id].clone();
+
+        // Silent by default
+        manager
+            .join_conference(
+                room_id,
+                "call-1".to_string(),
+                "Alice".to_string(),
+                ParticipantRole::Attendee,
+                None,
+            )
+            .await
+            .unwrap();
+        assert!(!mixer.is_announcing());
+
+        let settings = EntryExitSettings {
+            cue: EntryExitCue::Name,
+            name_threshold: 2,
+            ..Default::default()
+        };
+        manager.set_entry_exit(room_id, settings).await.unwrap();
+
+        let prompt = manager
+            .record_name_prompt(room_id, "call-2")
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(prompt.audio_files, vec!["conf_record_name".to_string()]);
+        manager
+            .join_conference(
+                room_id,
+                "call-2".to_string(),
+                "Bob".to_string(),
+                ParticipantRole::Attendee,
+                None,
+            )
+            .await
+            .unwrap();
+        // Without a recorded name the join is announced with a tone
+        assert!(mixer.is_announcing());
+
+        // Above the threshold names are no longer recorded
+        assert!(manager
+            .record_name_prompt(room_id, "call-3")
+            .await
+            .unwrap()
+            .is_none());
+    }
+
     #[tokio::test]
     async fn test_active_conference_count() {
         let manager = ConferenceManager::new();
@@ -0,0 +1,551 @@
+//! Softphone enrollment
+//!
+//! An admin (or the user) creates an enrollment for a user and hands the
+//! link or QR code to the softphone. Opening the link consumes the token
+//! and returns the connection details with a freshly generated password.
+//! That password belongs to a new device credential of the user: the
+//! user's own password is never handed out, and revoking the device cuts it
+//! off at its next REGISTER. Only SHA-256 digests of the tokens are kept.
+
+use crate::domain::user::UserRepository;
+use chrono::{DateTime, Duration, Utc};
+use rand::distributions::Alphanumeric;
+use rand::{Rng, RngCore};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::sync::{Arc, Mutex};
+use tracing::{info, warn};
+use uuid::Uuid;
+
+/// Length of generated device passwords
+const DEVICE_PASSWORD_LEN: usize = 24;
+
+/// Transports a softphone can be told to use
+const TRANSPORTS: [&str; 5] = ["udp", "tcp", "tls", "ws", "wss"];
+
+/// Connection details handed out and limits of enrollment tokens
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct EnrollmentConfig {
+    /// SIP server the softphones register to (defaults to the SIP domain)
+    pub server: Option<String>,
+    /// SIP port (defaults to the bind port)
+    pub port: Option<u16>,
+    pub transport: String,
+    /// Base URL of the API as seen by the phones, e.g.
+    /// `https://pbx.example.com`; links are relative without it
+    pub public_url: Option<String>,
+    /// Lifetime of a token unless the request asks for less
+    pub default_ttl_secs: u64,
+    pub max_ttl_secs: u64,
+    /// Most devices one token may enroll
+    pub max_uses: u32,
+    /// Redemption attempts per client address and minute
+    pub requests_per_minute: u32,
+    /// Reverse proxies whose `X-Forwarded-For` names the client; anyone
+    /// else is limited by its own address
+    pub trusted_proxies: Vec<IpAddr>,
+}
+
+impl Default for EnrollmentConfig {
+    fn default() -> Self {
+        Self {
+            server: None,
+            port: None,
+            transport: "udp".to_string(),
+            public_url: None,
+            default_ttl_secs: 86_400,
+            max_ttl_secs: 7 * 86_400,
+            max_uses: 10,
+            requests_per_minute: 10,
+            trusted_proxies: Vec::new(),
+        }
+    }
+}
+
+impl EnrollmentConfig {
+    pub fn validate(&self) -> Result<(), String> {
+        if !TRANSPORTS.contains(&self.transport.as_str()) {
+            return Err(format!(
+                "transport must be one of {}, got '{}'",
+                TRANSPORTS.join(", "),
+                self.transport
+            ));
+        }
+        if self.default_ttl_secs == 0 || self.default_ttl_secs > self.max_ttl_secs {
+            return Err("default_ttl_secs must be between 1 and max_ttl_secs".to_string());
+        }
+        if self.max_uses == 0 {
+            return Err("max_uses must be at least 1".to_string());
+        }
+        if self.requests_per_minute == 0 {
+            return Err("requests_per_minute must be at least 1".to_string());
+        }
+        if let Some(url) = &self.public_url {
+            if !url.starts_with("http://") && !url.starts_with("https://") {
+                return Err(format!("public_url must be an http(s) URL, got '{}'", url));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// One-time (or few-times) token enrolling devices of a user
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Enrollment {
+    pub id: Uuid,
+    pub user_id: i32,
+    /// Hex SHA-256 of the token
+    #[serde(skip_serializing)]
+    pub token_hash: String,
+    /// Given to the devices it enrolls, e.g. "Alice's iPhone"
+    pub label: Option<String>,
+    pub max_uses: u32,
+    pub uses: u32,
+    pub expires_at: DateTime<Utc>,
+    pub created_at: DateTime<Utc>,
+}
+
+impl Enrollment {
+    /// Whether the token still enrolls devices at `now`
+    pub fn is_redeemable(&self, now: DateTime<Utc>) -> bool {
+        self.uses < self.max_uses && now < self.expires_at
+    }
+}
+
+/// Extra SIP credential of a user, bound to one enrolled device
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DeviceCredential {
+    /// Device identity
+    pub id: Uuid,
+    pub user_id: i32,
+    pub enrollment_id: Option<Uuid>,
+    pub label: Option<String>,
+    /// MD5(username:realm:device password)
+    #[serde(skip_serializing)]
+    pub sip_ha1: String,
+    pub created_at: DateTime<Utc>,
+    pub revoked_at: Option<DateTime<Utc>>,
+}
+
+impl DeviceCredential {
+    pub fn is_active(&self) -> bool {
+        self.revoked_at.is_none()
+    }
+}
+
+/// Enrollment and device credential repository trait
+#[async_trait::async_trait]
+pub trait DeviceEnrollmentRepository: Send + Sync {
+    async fn create_enrollment(&self, enrollment: &Enrollment) -> Result<(), String>;
+
+    /// Count one use of the enrollment with `token_hash` if it is still
+    /// redeemable at `at`; `None` when it is unknown, expired or used up
+    async fn redeem(&self, token_hash: &str, at: DateTime<Utc>) -> Result<Option<Enrollment>, String>;
+
+    async fn create_credential(&self, credential: &DeviceCredential) -> Result<(), String>;
+
+    /// Credentials of a user, revoked ones included, oldest first
+    async fn list_credentials(&self, user_id: i32) -> Result<Vec<DeviceCredential>, String>;
+
+    /// Revoke an active credential of a user; false when there is none
+    async fn revoke_credential(&self, user_id: i32, id: Uuid, at: DateTime<Utc>) -> Result<bool, String>;
+}
+
+/// Enrollment errors
+#[derive(Debug, Clone, PartialEq)]
+pub enum EnrollmentError {
+    Invalid(String),
+    UserNotFound(i32),
+    DeviceNotFound(Uuid),
+    /// Unknown, expired or used up
+    InvalidToken,
+    RateLimited,
+    Repository(String),
+}
+
+impl std::fmt::Display for EnrollmentError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            EnrollmentError::Invalid(e) => write!(f, "{}", e),
+            EnrollmentError::UserNotFound(id) => write!(f, "User not found: {}", id),
+            EnrollmentError::DeviceNotFound(id) => write!(f, "Device not found: {}", id),
+            EnrollmentError::InvalidToken => {
+                write!(f, "Enrollment token is invalid, expired or already used")
+            }
+            EnrollmentError::RateLimited => write!(f, "Too many enrollment attempts"),
+            EnrollmentError::Repository(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl std::error::Error for EnrollmentError {}
+
+/// Create enrollment request
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct CreateEnrollment {
+    pub label: Option<String>,
+    /// Token lifetime; the configured default without it
+    pub ttl_secs: Option<u64>,
+    /// Devices the token may enroll; one without it
+    pub max_uses: Option<u32>,
+}
+
+/// A new enrollment with its token, shown once
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct IssuedEnrollment {
+    pub enrollment: Enrollment,
+    pub token: String,
+    /// Link the softphone opens to enroll
+    pub url: String,
+    /// String to render as QR code
+    pub qr_payload: String,
+}
+
+/// What an enrolled softphone needs to register
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SipCredentialBundle {
+    pub device_id: Uuid,
+    pub label: Option<String>,
+    pub server: String,
+    pub port: u16,
+    pub transport: String,
+    pub realm: String,
+    pub username: String,
+    pub display_name: Option<String>,
+    /// Password of this device only
+    pub password: String,
+}
+
+/// Issues enrollments and turns them into device credentials
+pub struct EnrollmentService {
+    repository: Arc<dyn DeviceEnrollmentRepository>,
+    users: Arc<dyn UserRepository>,
+    config: EnrollmentConfig,
+    sip_server: String,
+    sip_port: u16,
+    /// Redemption attempts per client address in the current minute
+    attempts: Mutex<HashMap<String, (u32, DateTime<Utc>)>>,
+}
+
+impl EnrollmentService {
+    pub fn new(
+        repository: Arc<dyn DeviceEnrollmentRepository>,
+        users: Arc<dyn UserRepository>,
+        config: EnrollmentConfig,
+    ) -> Self {
+        Self {
+            repository,
+            users,
+            config,
+            sip_server: "localhost".to_string(),
+            sip_port: 5060,
+            attempts: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Server and port handed out unless configured otherwise
+    pub fn with_sip_server(mut self, server: String, port: u16) -> Self {
+        self.sip_server = server;
+        self.sip_port = port;
+        self
+    }
+
+    fn server(&self) -> (String, u16) {
+        (
+            self.config.server.clone().unwrap_or_else(|| self.sip_server.clone()),
+            self.config.port.unwrap_or(self.sip_port),
+        )
+    }
+
+    fn digest(token: &str) -> String {
+        hex::encode(Sha256::digest(token.as_bytes()))
+    }
+
+    /// Create an enrollment for a user
+    pub async fn create(
+        &self,
+        user_id: i32,
+        request: CreateEnrollment,
+    ) -> Result<IssuedEnrollment, EnrollmentError> {
+        self.create_at(user_id, request, Utc::now()).await
+    }
+
+    pub async fn create_at(
+        &self,
+        user_id: i32,
+        request: CreateEnrollment,
+        now: DateTime<Utc>,
+    ) -> Result<IssuedEnrollment, EnrollmentError> {
+        let ttl_secs = request.ttl_secs.unwrap_or(self.config.default_ttl_secs);
+        if ttl_secs == 0 || ttl_secs > self.config.max_ttl_secs {
+            return Err(EnrollmentError::Invalid(format!(
+                "ttl_secs must be between 1 and {}",
+                self.config.max_ttl_secs
+            )));
+        }
+        let max_uses = request.max_uses.unwrap_or(1);
+        if max_uses == 0 || max_uses > self.config.max_uses {
+            return Err(EnrollmentError::Invalid(format!(
+                "max_uses must be between 1 and {}",
+                self.config.max_uses
+            )));
+        }
+
+        self.users
+            .find_by_id(user_id)
+            .await
+            .map_err(|e| EnrollmentError::Repository(e.to_string()))?
+            .ok_or(EnrollmentError::UserNotFound(user_id))?;
+
+        let mut bytes = [0u8; 32];
+        rand::thread_rng().fill_bytes(&mut bytes);
+        let token = hex::encode(bytes);
+
+        let enrollment = Enrollment {
+            id: Uuid::new_v4(),
+            user_id,
+            token_hash: Self::digest(&token),
+            label: request.label,
+            max_uses,
+            uses: 0,
+            expires_at: now + Duration::seconds(ttl_secs as i64),
+            created_at: now,
+        };
+        self.repository
+            .create_enrollment(&enrollment)
+            .await
+            .map_err(EnrollmentError::Repository)?;
+
+        let base = self.config.public_url.as_deref().unwrap_or("");
+        let url = format!("{}/enroll/{}", base.trim_end_matches('/'), token);
+        let (server, port) = self.server();
+        let qr_payload = serde_json::json!({
+            "server": server,
+            "port": port,
+            "transport": self.config.transport,
+            "enroll": url,
+        })
+        .to_string();
+
+        info!(
+            "Created enrollment {} for user {} ({} uses, expires {})",
+            enrollment.id, user_id, max_uses, enrollment.expires_at
+        );
+        Ok(IssuedEnrollment {
+            enrollment,
+            token,
+            url,
+            qr_payload,
+        })
+    }
+
+    /// Address an attempt is limited by: the peer, or the first
+    /// `X-Forwarded-For` entry when the peer is a trusted proxy
+    pub fn client_address(&self, peer: Option<IpAddr>, forwarded_for: Option<&str>) -> String {
+        let Some(peer) = peer else {
+            return "unknown".to_string();
+        };
+        if self.config.trusted_proxies.contains(&peer) {
+            if let Some(client) = forwarded_for
+                .and_then(|v| v.split(',').next())
+                .and_then(|v| v.trim().parse::<IpAddr>().ok())
+            {
+                return client.to_string();
+            }
+        }
+        peer.to_string()
+    }
+
+    /// Count an attempt of `client`; false once it made too many this minute
+    fn admit(&self, client: &str, now: DateTime<Utc>) -> bool {
+        let mut attempts = self.attempts.lock().unwrap();
+        attempts.retain(|_, (_, window)| now - *window < Duration::minutes(1));
+        let (count, _) = attempts.entry(client.to_string()).or_insert((0, now));
+        *count += 1;
+        *count <= self.config.requests_per_minute
+    }
+
+    /// Consume a token and create a credential for the new device
+    pub async fn redeem(
+        &self,
+        token: &str,
+        client: &str,
+    ) -> Result<SipCredentialBundle, EnrollmentError> {
+        self.redeem_at(token, client, Utc::now()).await
+    }
+
+    pub async fn redeem_at(
+        &self,
+        token: &str,
+        client: &str,
+        now: DateTime<Utc>,
+    ) -> Result<SipCredentialBundle, EnrollmentError> {
+        if !self.admit(client, now) {
+            warn!("Enrollment attempts from {} rate limited", client);
+            return Err(EnrollmentError::RateLimited);
+        }
+
+        let enrollment = self
+            .repository
+            .redeem(&Self::digest(token), now)
+            .await
+            .map_err(EnrollmentError::Repository)?
+            .ok_or_else(|| {
+                warn!("Invalid enrollment token presented by {}", client);
+                EnrollmentError::InvalidToken
+            })?;
+
+        let user = self
+            .users
+            .find_by_id(enrollment.user_id)
+            .await
+            .map_err(|e| EnrollmentError::Repository(e.to_string()))?
+            .filter(|u| u.is_enabled())
+            .ok_or(EnrollmentError::InvalidToken)?;
+
+        let password: String = rand::thread_rng()
+            .sample_iter(&Alphanumeric)
+            .take(DEVICE_PASSWORD_LEN)
+            .map(char::from)
+            .collect();
+        let credential = DeviceCredential {
+            id: Uuid::new_v4(),
+            user_id: user.id,
+            enrollment_id: Some(enrollment.id),
+            label: enrollment.label.clone(),
+            sip_ha1: format!(
+                "{:x}",
+                md5::compute(format!("{}:{}:{}", user.username, user.realm, password))
+            ),
+            created_at: now,
+            revoked_at: None,
+        };
+        self.repository
+            .create_credential(&credential)
+            .await
+            .map_err(EnrollmentError::Repository)?;
+
+        info!(
+            "Enrolled device {} of user {} from {}",
+            credential.id, user.username, client
+        );
+        let (server, port) = self.server();
+        Ok(SipCredentialBundle {
+            device_id: credential.id,
+            label: credential.label,
+            server,
+            port,
+            transport: self.config.transport.clone(),
+            realm: user.realm,
+            username: user.username,
+            display_name: user.display_name,
+            password,
+        })
+    }
+
+    /// Devices of a user, revoked ones included
+    pub async fn devices(&self, user_id: i32) -> Result<Vec<DeviceCredential>, EnrollmentError> {
+        self.repository
+            .list_credentials(user_id)
+            .await
+            .map_err(EnrollmentError::Repository)
+    }
+
+    /// Revoke a device; it can no longer register
+    pub async fn revoke(&self, user_id: i32, device_id: Uuid) -> Result<(), EnrollmentError> {
+        let revoked = self
+            .repository
+            .revoke_credential(user_id, device_id, Utc::now())
+            .await
+            .map_err(EnrollmentError::Repository)?;
+        if !revoked {
+            return Err(EnrollmentError::DeviceNotFound(device_id));
+        }
+        info!("Revoked device {} of user {}", device_id, user_id);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::domain::user::repository::MockUserRepository;
+    use crate::infrastructure::persistence::MemoryDeviceEnrollmentRepository;
+
+    #[test]
+    fn test_config_validate() {
+        assert!(EnrollmentConfig::default().validate().is_ok());
+
+        let config = EnrollmentConfig {
+            transport: "sctp".to_string(),
+            ..Default::default()
+        };
+        assert!(config.validate().unwrap_err().contains("sctp"));
+
+        let config = EnrollmentConfig {
+            default_ttl_secs: 30 * 86_400,
+            ..Default::default()
+        };
+        assert!(config.validate().is_err());
+
+        let config = EnrollmentConfig {
+            public_url: Some("pbx.example.com".to_string()),
+            ..Default::default()
+        };
+        assert!(config.validate().is_err());
+    }
+
+    #[test]
+    fn test_forwarded_for_trusted_only_from_proxies() {
+        let proxy: IpAddr = "10.0.0.1".parse().unwrap();
+        let stranger: IpAddr = "203.0.113.7".parse().unwrap();
+        let service = EnrollmentService::new(
+            Arc::new(MemoryDeviceEnrollmentRepository::new()),
+            Arc::new(MockUserRepository::new()),
+            EnrollmentConfig {
+                requests_per_minute: 2,
+                trusted_proxies: vec![proxy],
+                ..Default::default()
+            },
+        );
+
+        assert_eq!(
+            service.client_address(Some(proxy), Some("198.51.100.4, 10.0.0.1")),
+            "198.51.100.4"
+        );
+        assert_eq!(service.client_address(Some(proxy), Some("junk")), "10.0.0.1");
+        assert_eq!(service.client_address(None, Some("198.51.100.4")), "unknown");
+
+        // Rotating the header does not get a direct client past the limit
+        let now = Utc::now();
+        for forwarded in ["1.1.1.1", "2.2.2.2", "3.3.3.3"] {
+            let client = service.client_address(Some(stranger), Some(forwarded));
+            assert_eq!(client, "203.0.113.7");
+            service.admit(&client, now);
+        }
+        assert!(!service.admit(&service.client_address(Some(stranger), None), now));
+    }
+
+    #[test]
+    fn test_redeemable() {
+        let now = Utc::now();
+        let mut enrollment = Enrollment {
+            id: Uuid::new_v4(),
+            user_id: 1,
+            token_hash: String::new(),
+            label: None,
+            max_uses: 1,
+            uses: 0,
+            expires_at: now + Duration::hours(1),
+            created_at: now,
+        };
+        assert!(enrollment.is_redeemable(now));
+        assert!(!enrollment.is_redeemable(now + Duration::hours(1)));
+
+        enrollment.uses = 1;
+        assert!(!enrollment.is_redeemable(now));
+    }
+}
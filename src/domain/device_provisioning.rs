@@ -0,0 +1,158 @@
+//! Provisioned devices and configuration resync
+//!
+//! Phones listed in `provisioning.devices` fetch their configuration from
+//! `/provisioning/{mac}`. When something the configuration carries changes
+//! (the user's speed dials or display name), or when an admin asks for it,
+//! the phone is sent a check-sync NOTIFY so it fetches the configuration
+//! again without a reboot. The NOTIFY itself is sent by the SIP layer's
+//! `DeviceResyncer`; this module holds the device profiles and their
+//! resync state as reported by the API.
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+
+/// Phone vendor, which decides the resync NOTIFY and the config format
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum DeviceVendor {
+    Yealink,
+    /// Poly (Polycom) VVX and later
+    Polycom,
+    /// Anything that understands `check-sync;reboot=false`
+    #[default]
+    Generic,
+}
+
+impl DeviceVendor {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            DeviceVendor::Yealink => "yealink",
+            DeviceVendor::Polycom => "polycom",
+            DeviceVendor::Generic => "generic",
+        }
+    }
+
+    /// `Event` header of the NOTIFY that makes the phone re-fetch its
+    /// configuration
+    ///
+    /// Polycom phones ignore the `reboot` parameter and only restart when
+    /// the new configuration needs it, so they get the bare event.
+    pub fn check_sync_event(&self) -> &'static str {
+        match self {
+            DeviceVendor::Yealink | DeviceVendor::Generic => "check-sync;reboot=false",
+            DeviceVendor::Polycom => "check-sync",
+        }
+    }
+}
+
+/// MAC address as 12 lowercase hex digits, with any `:`, `-` or `.`
+/// separators removed
+pub fn normalize_mac(mac: &str) -> Option<String> {
+    let hex: String = mac
+        .chars()
+        .filter(|c| !matches!(c, ':' | '-' | '.'))
+        .map(|c| c.to_ascii_lowercase())
+        .collect();
+    (hex.len() == 12 && hex.chars().all(|c| c.is_ascii_hexdigit())).then_some(hex)
+}
+
+/// A provisioned phone
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct DeviceProfile {
+    /// MAC address, in any common notation
+    pub mac: String,
+    #[serde(default)]
+    pub vendor: DeviceVendor,
+    /// User whose line the phone carries
+    pub username: String,
+    /// Realm (tenant) of the user
+    pub realm: String,
+}
+
+/// Provisioned devices and automatic resync
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ProvisioningConfig {
+    pub devices: Vec<DeviceProfile>,
+    /// Resync a user's phones when their speed dials or display name change
+    pub auto_resync: bool,
+    /// Quiet period after the last change before the NOTIFY goes out, so a
+    /// bulk edit causes one resync
+    pub resync_debounce_secs: u64,
+}
+
+impl Default for ProvisioningConfig {
+    fn default() -> Self {
+        Self {
+            devices: Vec::new(),
+            auto_resync: true,
+            resync_debounce_secs: 5,
+        }
+    }
+}
+
+impl ProvisioningConfig {
+    /// Every device has a valid, unique MAC address and a user
+    pub fn validate(&self) -> Result<(), String> {
+        let mut seen = HashSet::new();
+        for device in &self.devices {
+            let mac = normalize_mac(&device.mac)
+                .ok_or_else(|| format!("invalid MAC address '{}'", device.mac))?;
+            if !seen.insert(mac) {
+                return Err(format!("device {} is listed twice", device.mac));
+            }
+            if device.username.is_empty() {
+                return Err(format!("device {} has no username", device.mac));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Resync state of a device
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DeviceResyncStatus {
+    pub mac: String,
+    pub vendor: DeviceVendor,
+    pub username: String,
+    pub realm: String,
+    /// When the last check-sync NOTIFY was sent
+    pub last_resync_at: Option<DateTime<Utc>>,
+    /// Contacts the last NOTIFY was sent to
+    pub last_resync_contacts: usize,
+    /// A resync waits for the device to register
+    pub resync_pending: bool,
+    /// When the device last fetched its configuration
+    pub last_config_fetch_at: Option<DateTime<Utc>>,
+    /// The device fetched its configuration after the last resync
+    pub refetched: bool,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_normalize_mac_and_validate() {
+        assert_eq!(normalize_mac("80:5E:C0:AA:BB:CC").as_deref(), Some("805ec0aabbcc"));
+        assert_eq!(normalize_mac("0004.f211.2233").as_deref(), Some("0004f2112233"));
+        assert_eq!(normalize_mac("00-04-f2-11-22-33").as_deref(), Some("0004f2112233"));
+        assert!(normalize_mac("805ec0aabb").is_none());
+        assert!(normalize_mac("805ec0aabbzz").is_none());
+
+        let device = |mac: &str| DeviceProfile {
+            mac: mac.to_string(),
+            vendor: DeviceVendor::Yealink,
+            username: "alice".to_string(),
+            realm: "example.com".to_string(),
+        };
+        let mut config = ProvisioningConfig {
+            devices: vec![device("805ec0aabbcc"), device("0004f2112233")],
+            ..Default::default()
+        };
+        assert!(config.validate().is_ok());
+        config.devices.push(device("80:5e:c0:aa:bb:cc"));
+        assert!(config.validate().unwrap_err().contains("listed twice"));
+    }
+}
@@ -0,0 +1,142 @@
+//! Per-device access tokens
+//!
+//! Phones fetch resources such as the remote phonebook without a user's
+//! password: each device gets its own token, bound to the realm (tenant)
+//! the device belongs to. Only SHA-256 digests of the tokens are kept.
+
+use crate::domain::shared::SecretBox;
+use rand::RngCore;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::sync::RwLock;
+use tracing::info;
+
+/// Token of a provisioned device
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DeviceTokenConfig {
+    /// Device identifier, usually the MAC address
+    pub device_id: String,
+    /// Realm (tenant) the device belongs to
+    pub realm: String,
+    pub token: SecretBox,
+}
+
+/// Device a token was issued to
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DeviceIdentity {
+    pub device_id: String,
+    pub realm: String,
+}
+
+/// Tokens of all known devices
+#[derive(Default)]
+pub struct DeviceTokenStore {
+    tokens: RwLock<HashMap<[u8; 32], DeviceIdentity>>,
+}
+
+impl DeviceTokenStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn from_config(devices: &[DeviceTokenConfig]) -> Self {
+        let store = Self::new();
+        for device in devices {
+            store.insert(
+                device.token.expose(),
+                DeviceIdentity {
+                    device_id: device.device_id.clone(),
+                    realm: device.realm.clone(),
+                },
+            );
+        }
+        store
+    }
+
+    fn digest(token: &str) -> [u8; 32] {
+        Sha256::digest(token.as_bytes()).into()
+    }
+
+    /// Register an existing token
+    pub fn insert(&self, token: &str, identity: DeviceIdentity) {
+        self.tokens
+            .write()
+            .unwrap()
+            .insert(Self::digest(token), identity);
+    }
+
+    /// Create a random token for a device
+    pub fn issue(&self, device_id: &str, realm: &str) -> String {
+        let mut bytes = [0u8; 32];
+        rand::thread_rng().fill_bytes(&mut bytes);
+        let token = hex::encode(bytes);
+        self.insert(
+            &token,
+            DeviceIdentity {
+                device_id: device_id.to_string(),
+                realm: realm.to_string(),
+            },
+        );
+        info!("Issued device token for {} ({})", device_id, realm);
+        token
+    }
+
+    /// Device the token belongs to
+    pub fn verify(&self, token: &str) -> Option<DeviceIdentity> {
+        if token.is_empty() {
+            return None;
+        }
+        self.tokens
+            .read()
+            .unwrap()
+            .get(&Self::digest(token))
+            .cloned()
+    }
+
+    /// Remove every token of a device; returns how many were removed
+    pub fn revoke(&self, device_id: &str) -> usize {
+        let mut tokens = self.tokens.write().unwrap();
+        let before = tokens.len();
+        tokens.retain(|_, identity| identity.device_id != device_id);
+        before - tokens.len()
+    }
+
+    pub fn len(&self) -> usize {
+        self.tokens.read().unwrap().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_issue_verify_revoke() {
+        let store = DeviceTokenStore::from_config(&[DeviceTokenConfig {
+            device_id: "805ec0aabbcc".to_string(),
+            realm: "example.com".to_string(),
+            token: SecretBox::new("configured-token"),
+        }]);
+        let issued = store.issue("0004f2112233", "other.com");
+
+        assert_eq!(
+            store.verify("configured-token").map(|d| d.realm),
+            Some("example.com".to_string())
+        );
+        assert_eq!(
+            store.verify(&issued).map(|d| d.device_id),
+            Some("0004f2112233".to_string())
+        );
+        assert!(store.verify("wrong").is_none());
+        assert!(store.verify("").is_none());
+
+        assert_eq!(store.revoke("0004f2112233"), 1);
+        assert!(store.verify(&issued).is_none());
+        assert_eq!(store.len(), 1);
+    }
+}
@@ -3,10 +3,11 @@
 //! Provides Do Not Disturb functionality for users to block incoming calls
 //! with support for schedules, exceptions, and various rejection modes.
 
-use chrono::{DateTime, NaiveTime, Utc, Weekday};
+use chrono::{DateTime, Datelike, NaiveTime, Utc, Weekday};
 use serde::{Deserialize, Serialize};
 use std::collections::{HashMap, HashSet};
 use std::sync::{Arc, Mutex};
+use tokio::sync::broadcast;
 use uuid::Uuid;
 
 /// DND rejection mode - how to handle blocked calls
@@ -378,6 +379,13 @@ impl Default for DndStatistics {
     }
 }
 
+/// DND turned on or off for a user
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DndChange {
+    pub user_id: String,
+    pub enabled: bool,
+}
+
 /// Do Not Disturb manager
 pub struct DndManager {
     /// User DND status
@@ -388,6 +396,8 @@ pub struct DndManager {
     blocked_by_mode: Arc<Mutex<HashMap<String, u64>>>,
     /// Exception match counter
     exception_matches: Arc<Mutex<u64>>,
+    /// Manual DND changes
+    changes: broadcast::Sender<DndChange>,
 }
 
 impl DndManager {
@@ -397,9 +407,23 @@ impl DndManager {
             blocked_calls: Arc::new(Mutex::new(0)),
             blocked_by_mode: Arc::new(Mutex::new(HashMap::new())),
             exception_matches: Arc::new(Mutex::new(0)),
+            changes: broadcast::channel(64).0,
         }
     }
 
+    /// Subscribe to DND being turned on or off
+    pub fn subscribe(&self) -> broadcast::Receiver<DndChange> {
+        self.changes.subscribe()
+    }
+
+    fn notify(&self, user_id: &str, enabled: bool) {
+        // Nobody listening is fine
+        let _ = self.changes.send(DndChange {
+            user_id: user_id.to_string(),
+            enabled,
+        });
+    }
+
     /// Enable DND for a user
     pub fn enable_dnd(&self, user_id: &str, mode: DndMode, manual: bool) {
         let mut users = self.user_status.lock().unwrap();
@@ -411,6 +435,8 @@ impl DndManager {
         status.mode = mode;
         status.manual_override = manual;
         status.enabled_at = Some(Utc::now());
+        drop(users);
+        self.notify(user_id, true);
     }
 
     /// Disable DND for a user
@@ -420,6 +446,8 @@ impl DndManager {
             status.enabled = false;
             status.manual_override = false;
             status.disabled_at = Some(Utc::now());
+            drop(users);
+            self.notify(user_id, false);
         }
     }
 
@@ -439,7 +467,10 @@ impl DndManager {
             status.disabled_at = Some(Utc::now());
         }
 
-        status.enabled
+        let enabled = status.enabled;
+        drop(users);
+        self.notify(user_id, enabled);
+        enabled
     }
 
     /// Check if DND is enabled for a user
@@ -0,0 +1,860 @@
+//! Feature codes
+//!
+//! Star codes that invoke a PBX feature instead of calling a number: `*78`
+//! turns on do not disturb, `*97` calls the caller's mailbox. Every feature
+//! registers its default code and a handler with the
+//! [`FeatureCodeRegistry`]; the configuration moves codes and switches
+//! features off, for everyone or per tenant (keyed by SIP realm). Codes
+//! that could be confused when dialed are refused at registration.
+//!
+//! The INVITE handler asks the registry once, before speed dials and the
+//! dial plan. A star code that is neither a feature nor a speed dial gets an
+//! announcement instead of a 404.
+
+use crate::domain::call_pickup::PickupType;
+use crate::domain::dnd::{DndManager, DndMode};
+use crate::domain::routing::lint::{DialRule, Lint, RoutingInventory, FEATURE_CODE_STEP};
+use crate::domain::routing::simulation::{Explain, Explanation, RouteOutcome, RoutedCall};
+use crate::domain::tenant_branding::realm_of;
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::Arc;
+use tracing::info;
+
+/// Prefix of every feature code
+pub const FEATURE_CODE_PREFIX: char = '*';
+
+/// Maximum number of digits of a code after the prefix (arguments excluded)
+pub const FEATURE_CODE_MAX_DIGITS: usize = 4;
+
+/// Features with a built-in code
+pub mod features {
+    pub const DIRECTED_PICKUP: &str = "pickup.directed";
+    pub const GROUP_PICKUP: &str = "pickup.group";
+    pub const ANY_PICKUP: &str = "pickup.any";
+    pub const BLF_PICKUP: &str = "pickup.blf";
+    pub const PARK: &str = "park";
+    pub const DND_ON: &str = "dnd.on";
+    pub const DND_OFF: &str = "dnd.off";
+    pub const VOICEMAIL: &str = "voicemail";
+    pub const VOICEMAIL_GREETING: &str = "voicemail.greeting";
+    pub const WAKE_UP_CALL: &str = "wakeup";
+}
+
+/// A feature and the code that invokes it
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FeatureCode {
+    /// Stable feature name, e.g. `dnd.on`
+    pub feature: String,
+    /// Dialed code, e.g. `*78`
+    pub code: String,
+    /// Whether digits dialed after the code are the feature's argument
+    /// (`*8<extension>`)
+    pub takes_argument: bool,
+    pub description: String,
+}
+
+impl FeatureCode {
+    /// Code dialed exactly as is
+    pub fn exact(feature: &str, code: &str, description: &str) -> Self {
+        Self {
+            feature: feature.to_string(),
+            code: code.to_string(),
+            takes_argument: false,
+            description: description.to_string(),
+        }
+    }
+
+    /// Code followed by an argument of one or more digits
+    pub fn with_argument(feature: &str, code: &str, description: &str) -> Self {
+        Self {
+            takes_argument: true,
+            ..Self::exact(feature, code, description)
+        }
+    }
+
+    /// The argument when `dialed` invokes this code (`Some("")` for exact
+    /// codes), `None` when it does not
+    pub fn matches<'a>(&self, dialed: &'a str) -> Option<&'a str> {
+        let rest = dialed.strip_prefix(self.code.as_str())?;
+        if !self.takes_argument {
+            return rest.is_empty().then_some(rest);
+        }
+        (!rest.is_empty() && rest.chars().all(|c| c.is_ascii_digit())).then_some(rest)
+    }
+
+    /// Whether a dialed string could be meant for either code
+    pub fn collides_with(&self, other: &FeatureCode) -> bool {
+        match (self.takes_argument, other.takes_argument) {
+            (false, false) => self.code == other.code,
+            (true, false) => other.code.starts_with(&self.code),
+            (false, true) => self.code.starts_with(&other.code),
+            (true, true) => self.code.starts_with(&other.code) || other.code.starts_with(&self.code),
+        }
+    }
+}
+
+/// Whether a dialed string has the shape of a feature code
+pub fn is_feature_code_pattern(dialed: &str) -> bool {
+    match dialed.strip_prefix(FEATURE_CODE_PREFIX) {
+        Some(digits) => !digits.is_empty() && digits.chars().all(|c| c.is_ascii_digit()),
+        None => false,
+    }
+}
+
+fn validate_code(code: &str) -> Result<(), FeatureCodeError> {
+    let valid = is_feature_code_pattern(code) && code.len() - 1 <= FEATURE_CODE_MAX_DIGITS;
+    if valid {
+        Ok(())
+    } else {
+        Err(FeatureCodeError::InvalidCode(code.to_string()))
+    }
+}
+
+/// The built-in features with their default codes
+///
+/// Speed dials may not use these codes, whether or not the feature is
+/// registered or moved by a tenant.
+pub fn standard_feature_codes() -> Vec<FeatureCode> {
+    let mut codes: Vec<FeatureCode> = PickupType::all()
+        .iter()
+        .map(|pickup| {
+            let feature = match pickup {
+                PickupType::Directed => features::DIRECTED_PICKUP,
+                PickupType::Group => features::GROUP_PICKUP,
+                PickupType::Any => features::ANY_PICKUP,
+                PickupType::Blf => features::BLF_PICKUP,
+            };
+            if pickup.takes_extension() {
+                FeatureCode::with_argument(feature, pickup.dial_code(), pickup.description())
+            } else {
+                FeatureCode::exact(feature, pickup.dial_code(), pickup.description())
+            }
+        })
+        .collect();
+    codes.extend([
+        FeatureCode::exact(features::PARK, "*70", "Park the current call"),
+        FeatureCode::with_argument(features::WAKE_UP_CALL, "*77", "Wake-up call at HHMM"),
+        FeatureCode::exact(features::DND_ON, "*78", "Do not disturb on"),
+        FeatureCode::exact(features::DND_OFF, "*79", "Do not disturb off"),
+        FeatureCode::exact(features::VOICEMAIL, "*97", "Own voicemail box"),
+        FeatureCode::exact(features::VOICEMAIL_GREETING, "*98", "Record voicemail greeting"),
+    ]);
+    codes
+}
+
+/// Default code of a built-in feature
+pub fn standard_feature_code(feature: &str) -> Option<FeatureCode> {
+    standard_feature_codes()
+        .into_iter()
+        .find(|code| code.feature == feature)
+}
+
+/// A call dialing a feature code
+#[derive(Debug, Clone, PartialEq)]
+pub struct FeatureCall {
+    pub call_id: String,
+    /// Username of the caller
+    pub caller: String,
+    /// Caller's SIP realm
+    pub realm: Option<String>,
+    /// Host the code was dialed at
+    pub domain: String,
+    /// The code as dialed
+    pub dialed: String,
+    /// Digits dialed after the code; empty for exact codes
+    pub argument: String,
+}
+
+/// What to do with a call that dialed a feature code
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum FeatureOutcome {
+    /// Route the call on to this URI
+    Route(String),
+    /// Answer the INVITE with this status
+    Respond(u16),
+    /// Answer the call, play this prompt and hang up
+    Announce(String),
+}
+
+/// Runs a feature for a call that dialed its code
+#[async_trait]
+pub trait FeatureCodeHandler: Send + Sync {
+    async fn invoke(&self, call: &FeatureCall) -> FeatureOutcome;
+}
+
+/// Feature code registration and configuration errors
+#[derive(Debug, Clone, PartialEq)]
+pub enum FeatureCodeError {
+    /// Code does not match the feature code pattern
+    InvalidCode(String),
+    /// Feature registered twice
+    Duplicate(String),
+    /// Configuration names a feature nobody registered
+    UnknownFeature(String),
+    /// Two enabled codes could be confused when dialed
+    Collision {
+        feature: String,
+        code: String,
+        existing_feature: String,
+        existing_code: String,
+        /// Tenant whose overrides collide; `None` for the defaults
+        realm: Option<String>,
+    },
+}
+
+impl std::fmt::Display for FeatureCodeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            FeatureCodeError::InvalidCode(code) => write!(
+                f,
+                "Invalid feature code '{}': expected '{}' followed by 1-{} digits",
+                code, FEATURE_CODE_PREFIX, FEATURE_CODE_MAX_DIGITS
+            ),
+            FeatureCodeError::Duplicate(feature) => {
+                write!(f, "Feature '{}' is already registered", feature)
+            }
+            FeatureCodeError::UnknownFeature(feature) => {
+                write!(f, "Unknown feature '{}'", feature)
+            }
+            FeatureCodeError::Collision {
+                feature,
+                code,
+                existing_feature,
+                existing_code,
+                realm,
+            } => {
+                write!(
+                    f,
+                    "Code '{}' of feature '{}' collides with code '{}' of feature '{}'",
+                    code, feature, existing_code, existing_feature
+                )?;
+                match realm {
+                    Some(realm) => write!(f, " in tenant {}", realm),
+                    None => Ok(()),
+                }
+            }
+        }
+    }
+}
+
+impl std::error::Error for FeatureCodeError {}
+
+/// Code and switch of one feature; unset fields are inherited
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct FeatureCodeOverride {
+    pub code: Option<String>,
+    pub enabled: Option<bool>,
+}
+
+/// Feature code configuration
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct FeatureCodeConfig {
+    /// Overrides for every tenant, by feature name
+    pub features: HashMap<String, FeatureCodeOverride>,
+    /// Per-tenant overrides, keyed by SIP realm, over `features`
+    pub tenants: HashMap<String, HashMap<String, FeatureCodeOverride>>,
+    /// Prompt for star codes that are neither a feature nor a speed dial;
+    /// without one such calls are answered 404
+    pub unknown_code_announcement: Option<String>,
+    /// Seconds a call hearing a feature announcement stays up
+    pub announcement_secs: u64,
+    /// Mailbox of `*97`; `{user}` is the caller, `{domain}` the dialed host
+    pub voicemail_uri: String,
+    /// Greeting recording of `*98`
+    pub greeting_uri: String,
+}
+
+impl Default for FeatureCodeConfig {
+    fn default() -> Self {
+        Self {
+            features: HashMap::new(),
+            tenants: HashMap::new(),
+            unknown_code_announcement: Some("feature-code-unknown".to_string()),
+            announcement_secs: 8,
+            voicemail_uri: "sip:vm-{user}@{domain}".to_string(),
+            greeting_uri: "sip:vm-greeting-{user}@{domain}".to_string(),
+        }
+    }
+}
+
+impl FeatureCodeConfig {
+    /// Codes that do not match the feature code pattern
+    pub fn validate(&self) -> Result<(), FeatureCodeError> {
+        let overrides = self
+            .features
+            .values()
+            .chain(self.tenants.values().flat_map(|features| features.values()));
+        for code in overrides.filter_map(|o| o.code.as_deref()) {
+            validate_code(code)?;
+        }
+        Ok(())
+    }
+}
+
+/// A feature code as it applies to one tenant
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct EffectiveFeatureCode {
+    pub feature: String,
+    pub code: String,
+    pub takes_argument: bool,
+    pub description: String,
+    pub enabled: bool,
+}
+
+struct RegisteredFeature {
+    code: FeatureCode,
+    handler: Arc<dyn FeatureCodeHandler>,
+}
+
+/// A feature matched by a dialed string
+pub struct FeatureMatch {
+    pub code: EffectiveFeatureCode,
+    pub argument: String,
+    handler: Arc<dyn FeatureCodeHandler>,
+}
+
+impl FeatureMatch {
+    /// Run the feature
+    pub async fn invoke(&self, call: &FeatureCall) -> FeatureOutcome {
+        self.handler.invoke(call).await
+    }
+}
+
+/// Registered features and their per-tenant codes
+pub struct FeatureCodeRegistry {
+    /// In registration order
+    features: Vec<RegisteredFeature>,
+    config: FeatureCodeConfig,
+}
+
+impl FeatureCodeRegistry {
+    pub fn new(config: FeatureCodeConfig) -> Self {
+        Self {
+            features: Vec::new(),
+            config,
+        }
+    }
+
+    /// Registry with the built-in features that have a handler here: do not
+    /// disturb and voicemail
+    pub fn standard(
+        config: FeatureCodeConfig,
+        dnd: Arc<DndManager>,
+    ) -> Result<Self, FeatureCodeError> {
+        Self::standard_with(config, dnd, Vec::new())
+    }
+
+    /// [`standard`](Self::standard) plus features handled elsewhere, e.g.
+    /// `*77` wake-up calls; the configuration may override those too
+    pub fn standard_with(
+        config: FeatureCodeConfig,
+        dnd: Arc<DndManager>,
+        extra: Vec<(FeatureCode, Arc<dyn FeatureCodeHandler>)>,
+    ) -> Result<Self, FeatureCodeError> {
+        let mut registry = Self::new(config);
+        let standard = |feature| standard_feature_code(feature).expect("built-in feature");
+        registry.register(
+            standard(features::DND_ON),
+            Arc::new(DndFeature { dnd: dnd.clone(), enable: true }),
+        )?;
+        registry.register(
+            standard(features::DND_OFF),
+            Arc::new(DndFeature { dnd, enable: false }),
+        )?;
+        let voicemail_uri = registry.config.voicemail_uri.clone();
+        let greeting_uri = registry.config.greeting_uri.clone();
+        registry.register(
+            standard(features::VOICEMAIL),
+            Arc::new(RouteFeature { uri_template: voicemail_uri }),
+        )?;
+        registry.register(
+            standard(features::VOICEMAIL_GREETING),
+            Arc::new(RouteFeature { uri_template: greeting_uri }),
+        )?;
+        for (code, handler) in extra {
+            registry.register(code, handler)?;
+        }
+        registry.check_config()?;
+        Ok(registry)
+    }
+
+    /// Register a feature with its default code
+    ///
+    /// Fails, leaving the registry unchanged, when the code (or a tenant's
+    /// override of it) could be confused with an enabled code of another
+    /// feature.
+    pub fn register(
+        &mut self,
+        code: FeatureCode,
+        handler: Arc<dyn FeatureCodeHandler>,
+    ) -> Result<(), FeatureCodeError> {
+        validate_code(&code.code)?;
+        if self.features.iter().any(|f| f.code.feature == code.feature) {
+            return Err(FeatureCodeError::Duplicate(code.feature));
+        }
+
+        let candidate = RegisteredFeature { code, handler };
+        let realms = std::iter::once(None).chain(self.config.tenants.keys().map(|r| Some(r.as_str())));
+        for realm in realms {
+            let new = self.effective(&candidate.code, realm);
+            if !new.enabled {
+                continue;
+            }
+            let new_code = FeatureCode {
+                code: new.code.clone(),
+                ..candidate.code.clone()
+            };
+            for existing in self.effective_codes(realm).into_iter().filter(|c| c.enabled) {
+                let existing_code = FeatureCode {
+                    feature: existing.feature.clone(),
+                    code: existing.code.clone(),
+                    takes_argument: existing.takes_argument,
+                    description: existing.description.clone(),
+                };
+                if new_code.collides_with(&existing_code) {
+                    return Err(FeatureCodeError::Collision {
+                        feature: new.feature,
+                        code: new.code,
+                        existing_feature: existing.feature,
+                        existing_code: existing.code,
+                        realm: realm.map(str::to_string),
+                    });
+                }
+            }
+        }
+
+        info!(
+            "Registered feature code {} for {}",
+            candidate.code.code, candidate.code.feature
+        );
+        self.features.push(candidate);
+        Ok(())
+    }
+
+    /// Check that the configuration only overrides registered features
+    pub fn check_config(&self) -> Result<(), FeatureCodeError> {
+        self.config.validate()?;
+        let named = self
+            .config
+            .features
+            .keys()
+            .chain(self.config.tenants.values().flat_map(|features| features.keys()));
+        for feature in named {
+            if !self.features.iter().any(|f| &f.code.feature == feature) {
+                return Err(FeatureCodeError::UnknownFeature(feature.clone()));
+            }
+        }
+        Ok(())
+    }
+
+    fn effective(&self, code: &FeatureCode, realm: Option<&str>) -> EffectiveFeatureCode {
+        let global = self.config.features.get(&code.feature);
+        let tenant = realm
+            .and_then(|realm| self.config.tenants.get(realm))
+            .and_then(|features| features.get(&code.feature));
+        let effective_code = tenant
+            .and_then(|o| o.code.clone())
+            .or_else(|| global.and_then(|o| o.code.clone()))
+            .unwrap_or_else(|| code.code.clone());
+        let enabled = tenant
+            .and_then(|o| o.enabled)
+            .or_else(|| global.and_then(|o| o.enabled))
+            .unwrap_or(true);
+        EffectiveFeatureCode {
+            feature: code.feature.clone(),
+            code: effective_code,
+            takes_argument: code.takes_argument,
+            description: code.description.clone(),
+            enabled,
+        }
+    }
+
+    /// Codes of every registered feature as they apply to a tenant
+    /// (`None` for callers without a configured tenant)
+    pub fn effective_codes(&self, realm: Option<&str>) -> Vec<EffectiveFeatureCode> {
+        self.features
+            .iter()
+            .map(|f| self.effective(&f.code, realm))
+            .collect()
+    }
+
+    /// Tenants with their own overrides
+    pub fn tenants(&self) -> Vec<String> {
+        let mut realms: Vec<String> = self.config.tenants.keys().cloned().collect();
+        realms.sort();
+        realms
+    }
+
+    /// The enabled feature a tenant's caller invokes by dialing `dialed`
+    pub fn lookup(&self, realm: Option<&str>, dialed: &str) -> Option<FeatureMatch> {
+        if !is_feature_code_pattern(dialed) {
+            return None;
+        }
+        self.features.iter().find_map(|f| {
+            let code = self.effective(&f.code, realm);
+            if !code.enabled {
+                return None;
+            }
+            let as_dialed = FeatureCode {
+                code: code.code.clone(),
+                ..f.code.clone()
+            };
+            let argument = as_dialed.matches(dialed)?.to_string();
+            Some(FeatureMatch {
+                code,
+                argument,
+                handler: f.handler.clone(),
+            })
+        })
+    }
+
+    /// Outcome for a star code that no feature or speed dial handles
+    pub fn unknown_code(&self) -> FeatureOutcome {
+        match &self.config.unknown_code_announcement {
+            Some(prompt) => FeatureOutcome::Announce(prompt.clone()),
+            None => FeatureOutcome::Respond(404),
+        }
+    }
+
+    /// Seconds a call hearing a feature announcement stays up
+    pub fn announcement_secs(&self) -> u64 {
+        self.config.announcement_secs
+    }
+
+    pub fn unknown_code_announcement(&self) -> Option<&str> {
+        self.config.unknown_code_announcement.as_deref()
+    }
+}
+
+#[async_trait]
+impl Explain for FeatureCodeRegistry {
+    fn step(&self) -> &'static str {
+        "feature_code"
+    }
+
+    async fn explain(&self, call: &RoutedCall) -> Option<Explanation> {
+        let feature = self.lookup(realm_of(&call.caller_uri), call.dialed())?;
+        Some(
+            Explanation::matched(format!("feature code {} ({})", feature.code.code, feature.code.feature))
+                .ending(RouteOutcome::Feature {
+                    feature: feature.code.feature,
+                }),
+        )
+    }
+}
+
+/// Star codes no feature or speed dial handles; explained after speed
+/// dials, as the live path answers them
+pub struct UnknownFeatureCode(pub Arc<FeatureCodeRegistry>);
+
+#[async_trait]
+impl Explain for UnknownFeatureCode {
+    fn step(&self) -> &'static str {
+        "unknown_feature_code"
+    }
+
+    async fn explain(&self, call: &RoutedCall) -> Option<Explanation> {
+        let dialed = call.dialed();
+        if !is_feature_code_pattern(dialed) {
+            return None;
+        }
+        let outcome = match self.0.unknown_code() {
+            FeatureOutcome::Announce(prompt) => RouteOutcome::Answered {
+                by: format!("announcement {}", prompt),
+            },
+            FeatureOutcome::Respond(status) => RouteOutcome::Rejected {
+                status,
+                reason: "unknown feature code".to_string(),
+            },
+            FeatureOutcome::Route(target) => RouteOutcome::Forward { target, trunk: None },
+        };
+        Some(Explanation::matched(format!("unknown code {}", dialed)).ending(outcome))
+    }
+}
+
+#[async_trait]
+impl Lint for FeatureCodeRegistry {
+    fn source(&self) -> &'static str {
+        FEATURE_CODE_STEP
+    }
+
+    /// Enabled codes for everyone, then the codes tenants move or switch
+    /// off
+    async fn inventory(&self, inventory: &mut RoutingInventory) {
+        let defaults = self.effective_codes(None);
+        for code in defaults.iter().filter(|code| code.enabled) {
+            inventory.rule(lint_rule(code));
+        }
+        for tenant in self.tenants() {
+            for (code, default) in self.effective_codes(Some(&tenant)).iter().zip(&defaults) {
+                if code == default {
+                    continue;
+                }
+                if code.enabled {
+                    inventory.rule(lint_rule(code).with_tenant(&tenant));
+                } else {
+                    inventory.disable(&lint_rule(code).id, &tenant);
+                }
+            }
+        }
+    }
+}
+
+fn lint_rule(code: &EffectiveFeatureCode) -> DialRule {
+    let id = format!("feature_code:{}", code.feature);
+    if code.takes_argument {
+        DialRule::prefix(id, &code.code)
+    } else {
+        DialRule::exact(id, &code.code)
+    }
+}
+
+/// `*78` / `*79`: do not disturb on or off for the caller
+struct DndFeature {
+    dnd: Arc<DndManager>,
+    enable: bool,
+}
+
+#[async_trait]
+impl FeatureCodeHandler for DndFeature {
+    async fn invoke(&self, call: &FeatureCall) -> FeatureOutcome {
+        if self.enable {
+            self.dnd.enable_dnd(&call.caller, DndMode::RejectBusy, true);
+            FeatureOutcome::Announce("dnd-enabled".to_string())
+        } else {
+            self.dnd.disable_dnd(&call.caller);
+            FeatureOutcome::Announce("dnd-disabled".to_string())
+        }
+    }
+}
+
+/// Routes the call to a URI made from the caller (`{user}`) and the dialed
+/// host (`{domain}`)
+struct RouteFeature {
+    uri_template: String,
+}
+
+#[async_trait]
+impl FeatureCodeHandler for RouteFeature {
+    async fn invoke(&self, call: &FeatureCall) -> FeatureOutcome {
+        FeatureOutcome::Route(
+            self.uri_template
+                .replace("{user}", &call.caller)
+                .replace("{domain}", &call.domain),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct Fixed(FeatureOutcome);
+
+    #[async_trait]
+    impl FeatureCodeHandler for Fixed {
+        async fn invoke(&self, _call: &FeatureCall) -> FeatureOutcome {
+            self.0.clone()
+        }
+    }
+
+    fn handler(status: u16) -> Arc<dyn FeatureCodeHandler> {
+        Arc::new(Fixed(FeatureOutcome::Respond(status)))
+    }
+
+    fn call(dialed: &str, argument: &str) -> FeatureCall {
+        FeatureCall {
+            call_id: "call-1".to_string(),
+            caller: "alice".to_string(),
+            realm: Some("acme.example.com".to_string()),
+            domain: "acme.example.com".to_string(),
+            dialed: dialed.to_string(),
+            argument: argument.to_string(),
+        }
+    }
+
+    fn acme_override(feature: &str, code: FeatureCodeOverride) -> FeatureCodeConfig {
+        let mut config = FeatureCodeConfig::default();
+        config
+            .tenants
+            .entry("acme.example.com".to_string())
+            .or_default()
+            .insert(feature.to_string(), code);
+        config
+    }
+
+    #[tokio::test]
+    async fn test_register_custom_code() {
+        let mut registry =
+            FeatureCodeRegistry::standard(FeatureCodeConfig::default(), Arc::new(DndManager::new()))
+                .unwrap();
+        registry
+            .register(
+                FeatureCode::with_argument("intercom", "*55", "Intercom to an extension"),
+                Arc::new(Fixed(FeatureOutcome::Route("sip:intercom@pbx".to_string()))),
+            )
+            .unwrap();
+
+        let found = registry.lookup(None, "*551001").unwrap();
+        assert_eq!(found.code.feature, "intercom");
+        assert_eq!(found.argument, "1001");
+        assert_eq!(
+            found.invoke(&call("*551001", "1001")).await,
+            FeatureOutcome::Route("sip:intercom@pbx".to_string())
+        );
+        // The argument is required
+        assert!(registry.lookup(None, "*55").is_none());
+
+        let voicemail = registry.lookup(None, "*97").unwrap();
+        assert_eq!(
+            voicemail.invoke(&call("*97", "")).await,
+            FeatureOutcome::Route("sip:vm-alice@acme.example.com".to_string())
+        );
+        assert!(registry.lookup(None, "*99").is_none());
+        assert!(registry.lookup(None, "1001").is_none());
+    }
+
+    #[tokio::test]
+    async fn test_tenant_overrides_default() {
+        let mut config = acme_override(
+            features::DND_ON,
+            FeatureCodeOverride {
+                code: Some("*44".to_string()),
+                enabled: None,
+            },
+        );
+        config.tenants.get_mut("acme.example.com").unwrap().insert(
+                features::VOICEMAIL_GREETING.to_string(),
+                FeatureCodeOverride {
+                    code: None,
+                    enabled: Some(false),
+                },
+            );
+        let dnd = Arc::new(DndManager::new());
+        let registry = FeatureCodeRegistry::standard(config, dnd.clone()).unwrap();
+
+        // Moved in acme only
+        let acme = Some("acme.example.com");
+        assert!(registry.lookup(acme, "*78").is_none());
+        let found = registry.lookup(acme, "*44").unwrap();
+        assert_eq!(found.code.feature, features::DND_ON);
+        assert_eq!(
+            found.invoke(&call("*44", "")).await,
+            FeatureOutcome::Announce("dnd-enabled".to_string())
+        );
+        assert!(dnd.is_enabled("alice"));
+        assert!(registry.lookup(None, "*78").is_some());
+        assert!(registry.lookup(Some("other.example.com"), "*44").is_none());
+
+        // Switched off in acme only
+        assert!(registry.lookup(acme, "*98").is_none());
+        assert!(registry.lookup(None, "*98").is_some());
+        let greeting = registry
+            .effective_codes(acme)
+            .into_iter()
+            .find(|c| c.feature == features::VOICEMAIL_GREETING)
+            .unwrap();
+        assert!(!greeting.enabled);
+    }
+
+    #[test]
+    fn test_collision_fails_registration() {
+        let mut registry = FeatureCodeRegistry::new(FeatureCodeConfig::default());
+        registry
+            .register(FeatureCode::with_argument("pickup", "*8", "Pickup"), handler(200))
+            .unwrap();
+
+        // Same code, and a code the argument form would swallow
+        for code in [
+            FeatureCode::exact("other", "*8", "Other"),
+            FeatureCode::exact("other", "*85", "Other"),
+            FeatureCode::with_argument("other", "*81", "Other"),
+        ] {
+            let err = registry.register(code, handler(200)).unwrap_err();
+            assert!(
+                matches!(&err, FeatureCodeError::Collision { existing_feature, .. } if existing_feature == "pickup"),
+                "{}",
+                err
+            );
+        }
+        assert_eq!(registry.effective_codes(None).len(), 1);
+
+        assert!(registry
+            .register(FeatureCode::exact("other", "*9", "Other"), handler(200))
+            .is_ok());
+        assert_eq!(
+            registry.register(FeatureCode::exact("other", "*90", "Other"), handler(200)),
+            Err(FeatureCodeError::Duplicate("other".to_string()))
+        );
+        assert!(matches!(
+            registry.register(FeatureCode::exact("bad", "78", "Bad"), handler(200)),
+            Err(FeatureCodeError::InvalidCode(_))
+        ));
+    }
+
+    #[test]
+    fn test_collision_with_tenant_override() {
+        // acme moved DND on to *97, where voicemail is
+        let config = acme_override(
+            features::DND_ON,
+            FeatureCodeOverride {
+                code: Some("*97".to_string()),
+                enabled: None,
+            },
+        );
+        let err = FeatureCodeRegistry::standard(config, Arc::new(DndManager::new()))
+            .err()
+            .unwrap();
+        assert!(matches!(
+            err,
+            FeatureCodeError::Collision { realm: Some(ref realm), .. } if realm == "acme.example.com"
+        ));
+
+        // No collision when acme switched voicemail off
+        let mut config = acme_override(
+            features::DND_ON,
+            FeatureCodeOverride {
+                code: Some("*97".to_string()),
+                enabled: None,
+            },
+        );
+        config.tenants.get_mut("acme.example.com").unwrap().insert(
+            features::VOICEMAIL.to_string(),
+            FeatureCodeOverride {
+                code: None,
+                enabled: Some(false),
+            },
+        );
+        assert!(FeatureCodeRegistry::standard(config, Arc::new(DndManager::new())).is_ok());
+
+        let config = acme_override(
+            "teleport",
+            FeatureCodeOverride {
+                code: Some("*12".to_string()),
+                enabled: None,
+            },
+        );
+        assert_eq!(
+            FeatureCodeRegistry::standard(config, Arc::new(DndManager::new())).err(),
+            Some(FeatureCodeError::UnknownFeature("teleport".to_string()))
+        );
+    }
+
+    #[test]
+    fn test_unknown_code_outcome() {
+        let registry = FeatureCodeRegistry::new(FeatureCodeConfig::default());
+        assert_eq!(
+            registry.unknown_code(),
+            FeatureOutcome::Announce("feature-code-unknown".to_string())
+        );
+        let registry = FeatureCodeRegistry::new(FeatureCodeConfig {
+            unknown_code_announcement: None,
+            ..Default::default()
+        });
+        assert_eq!(registry.unknown_code(), FeatureOutcome::Respond(404));
+    }
+}
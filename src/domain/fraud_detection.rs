@@ -0,0 +1,731 @@
+//! Toll fraud detection for outbound calls
+//!
+//! Outbound calls are checked against per-user velocity rules before they are
+//! routed: calls per destination prefix per hour, outbound minutes per day,
+//! destinations outside the allowed country list and concurrent international
+//! calls. Rules are configurable per tenant (keyed by SIP realm). A violation
+//! either warns (alert + audit entry), asks for a per-call confirmation
+//! announcement, or suspends the user's outbound calling until an admin
+//! clears it.
+//!
+//! Counters live in memory so evaluation never touches the database; the
+//! state is snapshotted to disk periodically so a restart does not reset an
+//! active suspension.
+
+use crate::domain::security::{SecurityAuditLogger, SecurityEvent, SecuritySeverity};
+use chrono::{DateTime, Duration, NaiveDate, Utc};
+use metrics::{counter, gauge};
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, RwLock};
+use tokio::sync::broadcast;
+use tokio::task::JoinHandle;
+use tracing::{error, info, warn};
+
+/// Number of violations kept per user for the status endpoint
+const RECENT_VIOLATIONS: usize = 20;
+
+/// What to do when a rule is violated (ordered by severity)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum FraudAction {
+    /// Raise an alert and audit entry, let the call through
+    Warn,
+    /// Play a confirmation announcement before connecting
+    Confirm,
+    /// Suspend outbound calling until cleared by an admin
+    Suspend,
+}
+
+impl FraudAction {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            FraudAction::Warn => "warn",
+            FraudAction::Confirm => "confirm",
+            FraudAction::Suspend => "suspend",
+        }
+    }
+}
+
+/// Fraud rule identifiers
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FraudRule {
+    PrefixVelocity,
+    DailyMinutes,
+    DisallowedCountry,
+    ConcurrentInternational,
+}
+
+impl FraudRule {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            FraudRule::PrefixVelocity => "prefix_velocity",
+            FraudRule::DailyMinutes => "daily_minutes",
+            FraudRule::DisallowedCountry => "disallowed_country",
+            FraudRule::ConcurrentInternational => "concurrent_international",
+        }
+    }
+}
+
+/// Threshold and action of a rule
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RuleLimit {
+    pub limit: u32,
+    pub action: FraudAction,
+}
+
+impl RuleLimit {
+    pub fn new(limit: u32, action: FraudAction) -> Self {
+        Self { limit, action }
+    }
+}
+
+/// Fraud rules for a tenant
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct FraudRules {
+    /// Country code of national numbers (no leading `+`)
+    pub home_country_code: String,
+    /// Minimum digits for a destination to count as an outbound number
+    pub min_outbound_digits: usize,
+    /// Digits of the E.164 number used as the velocity bucket
+    pub prefix_length: usize,
+    /// Calls per destination prefix per user per hour
+    pub calls_per_prefix_per_hour: Option<RuleLimit>,
+    /// Outbound minutes per user per day
+    pub outbound_minutes_per_day: Option<RuleLimit>,
+    /// Allowed country codes; `None` allows every country
+    pub allowed_countries: Option<Vec<String>>,
+    /// Action for destinations outside `allowed_countries`
+    pub disallowed_country_action: FraudAction,
+    /// Concurrent international calls per user
+    pub concurrent_international: Option<RuleLimit>,
+    /// Audio file played for `Confirm` actions
+    pub confirmation_prompt: String,
+}
+
+impl Default for FraudRules {
+    fn default() -> Self {
+        Self {
+            home_country_code: "1".to_string(),
+            min_outbound_digits: 7,
+            prefix_length: 6,
+            calls_per_prefix_per_hour: Some(RuleLimit::new(20, FraudAction::Suspend)),
+            outbound_minutes_per_day: Some(RuleLimit::new(480, FraudAction::Warn)),
+            allowed_countries: None,
+            disallowed_country_action: FraudAction::Confirm,
+            concurrent_international: Some(RuleLimit::new(5, FraudAction::Suspend)),
+            confirmation_prompt: "fraud_confirm".to_string(),
+        }
+    }
+}
+
+/// Outbound destination in E.164 form
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OutboundNumber {
+    /// Digits including country code, without `+`
+    pub e164: String,
+    pub international: bool,
+}
+
+impl FraudRules {
+    /// Classify a dialed destination; `None` for internal extensions
+    pub fn classify(&self, destination: &str) -> Option<OutboundNumber> {
+        let user = destination
+            .trim_start_matches("sips:")
+            .trim_start_matches("sip:")
+            .split('@')
+            .next()
+            .unwrap_or_default();
+
+        let (digits, explicit_international) = if let Some(rest) = user.strip_prefix('+') {
+            (rest, true)
+        } else if let Some(rest) = user.strip_prefix("011") {
+            (rest, true)
+        } else if let Some(rest) = user.strip_prefix("00") {
+            (rest, true)
+        } else {
+            (user, false)
+        };
+
+        if digits.len() < self.min_outbound_digits || !digits.chars().all(|c| c.is_ascii_digit()) {
+            return None;
+        }
+
+        let e164 = if explicit_international {
+            digits.to_string()
+        } else {
+            format!("{}{}", self.home_country_code, digits.trim_start_matches('0'))
+        };
+        let international = !e164.starts_with(&self.home_country_code);
+
+        Some(OutboundNumber {
+            e164,
+            international,
+        })
+    }
+}
+
+/// A rule violation
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct FraudViolation {
+    pub rule: FraudRule,
+    pub action: FraudAction,
+    pub detail: String,
+    pub at: DateTime<Utc>,
+}
+
+/// Alert published for every violation
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FraudAlert {
+    pub username: String,
+    pub call_id: String,
+    pub destination: String,
+    pub violation: FraudViolation,
+}
+
+/// Outcome of evaluating a call
+#[derive(Debug, Clone, PartialEq)]
+pub enum FraudDecision {
+    /// No rule matched, or the call is not outbound
+    Allow,
+    /// Call allowed, violations reported
+    Warn(Vec<FraudViolation>),
+    /// Call allowed after a confirmation announcement
+    Confirm(Vec<FraudViolation>),
+    /// Outbound calling is suspended for the user
+    Block(String),
+}
+
+/// Outbound calling suspension
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Suspension {
+    pub since: DateTime<Utc>,
+    pub reason: String,
+}
+
+/// Per-user counters (persisted)
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct UserFraudState {
+    /// Call start times per destination prefix, last hour only
+    pub prefix_calls: HashMap<String, VecDeque<DateTime<Utc>>>,
+    pub minutes_day: Option<NaiveDate>,
+    pub seconds_today: u64,
+    /// Call-IDs of international calls in progress
+    pub active_international: HashSet<String>,
+    pub suspended: Option<Suspension>,
+    pub recent_violations: VecDeque<FraudViolation>,
+}
+
+impl UserFraudState {
+    fn roll_day(&mut self, now: DateTime<Utc>) {
+        let today = now.date_naive();
+        if self.minutes_day != Some(today) {
+            self.minutes_day = Some(today);
+            self.seconds_today = 0;
+        }
+    }
+
+    fn prune(&mut self, now: DateTime<Utc>) {
+        let cutoff = now - Duration::hours(1);
+        self.prefix_calls.retain(|_, calls| {
+            calls.retain(|t| *t > cutoff);
+            !calls.is_empty()
+        });
+    }
+}
+
+/// Current counters of a user
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FraudStatus {
+    pub username: String,
+    pub suspended: bool,
+    pub suspension: Option<Suspension>,
+    /// Calls per destination prefix in the last hour
+    pub calls_per_prefix_last_hour: HashMap<String, usize>,
+    pub outbound_minutes_today: f64,
+    pub active_international_calls: usize,
+    pub recent_violations: Vec<FraudViolation>,
+}
+
+/// Outbound call to evaluate
+#[derive(Debug, Clone, Copy)]
+pub struct OutboundCall<'a> {
+    /// Tenant realm of the caller
+    pub realm: Option<&'a str>,
+    pub username: &'a str,
+    pub call_id: &'a str,
+    pub destination: &'a str,
+}
+
+/// Fraud detection service
+pub struct FraudDetector {
+    default_rules: FraudRules,
+    tenant_rules: RwLock<HashMap<String, FraudRules>>,
+    users: Mutex<HashMap<String, UserFraudState>>,
+    audit: Option<Arc<SecurityAuditLogger>>,
+    alerts: broadcast::Sender<FraudAlert>,
+}
+
+impl FraudDetector {
+    pub fn new(default_rules: FraudRules) -> Self {
+        let (alerts, _) = broadcast::channel(256);
+        Self {
+            default_rules,
+            tenant_rules: RwLock::new(HashMap::new()),
+            users: Mutex::new(HashMap::new()),
+            audit: None,
+            alerts,
+        }
+    }
+
+    /// Record violations in the security audit log
+    pub fn with_audit_logger(mut self, audit: Arc<SecurityAuditLogger>) -> Self {
+        self.audit = Some(audit);
+        self
+    }
+
+    /// Override rules for a tenant realm
+    pub fn set_tenant_rules(&self, realm: &str, rules: FraudRules) {
+        self.tenant_rules
+            .write()
+            .unwrap()
+            .insert(realm.to_string(), rules);
+    }
+
+    /// Rules in effect for a realm
+    pub fn rules_for(&self, realm: Option<&str>) -> FraudRules {
+        realm
+            .and_then(|r| self.tenant_rules.read().unwrap().get(r).cloned())
+            .unwrap_or_else(|| self.default_rules.clone())
+    }
+
+    /// Subscribe to fraud alerts
+    pub fn subscribe(&self) -> broadcast::Receiver<FraudAlert> {
+        self.alerts.subscribe()
+    }
+
+    /// Evaluate an outbound call before routing it
+    pub fn evaluate(&self, call: OutboundCall<'_>) -> FraudDecision {
+        self.evaluate_at(call, Utc::now())
+    }
+
+    fn evaluate_at(&self, call: OutboundCall<'_>, now: DateTime<Utc>) -> FraudDecision {
+        let rules = self.rules_for(call.realm);
+        let number = match rules.classify(call.destination) {
+            Some(number) => number,
+            None => return FraudDecision::Allow,
+        };
+
+        let (decision, violations) = {
+            let mut users = self.users.lock().unwrap();
+            let state = users.entry(call.username.to_string()).or_default();
+
+            if let Some(suspension) = &state.suspended {
+                counter!("fraud_blocked_calls_total").increment(1);
+                return FraudDecision::Block(suspension.reason.clone());
+            }
+
+            state.prune(now);
+            state.roll_day(now);
+
+            let prefix: String = number.e164.chars().take(rules.prefix_length).collect();
+            let mut violations = Vec::new();
+
+            if let Some(limit) = rules.calls_per_prefix_per_hour {
+                let calls = state.prefix_calls.get(&prefix).map_or(0, |c| c.len()) + 1;
+                if calls > limit.limit as usize {
+                    violations.push(FraudViolation {
+                        rule: FraudRule::PrefixVelocity,
+                        action: limit.action,
+                        detail: format!(
+                            "{} calls to +{} in the last hour (limit {})",
+                            calls, prefix, limit.limit
+                        ),
+                        at: now,
+                    });
+                }
+            }
+
+            if let Some(limit) = rules.outbound_minutes_per_day {
+                let minutes = state.seconds_today / 60;
+                if minutes >= limit.limit as u64 {
+                    violations.push(FraudViolation {
+                        rule: FraudRule::DailyMinutes,
+                        action: limit.action,
+                        detail: format!(
+                            "{} outbound minutes today (limit {})",
+                            minutes, limit.limit
+                        ),
+                        at: now,
+                    });
+                }
+            }
+
+            if let Some(allowed) = &rules.allowed_countries {
+                if !allowed.iter().any(|cc| number.e164.starts_with(cc.as_str())) {
+                    violations.push(FraudViolation {
+                        rule: FraudRule::DisallowedCountry,
+                        action: rules.disallowed_country_action,
+                        detail: format!("+{} is outside the allowed countries", number.e164),
+                        at: now,
+                    });
+                }
+            }
+
+            if number.international {
+                if let Some(limit) = rules.concurrent_international {
+                    let concurrent = state.active_international.len() + 1;
+                    if concurrent > limit.limit as usize {
+                        violations.push(FraudViolation {
+                            rule: FraudRule::ConcurrentInternational,
+                            action: limit.action,
+                            detail: format!(
+                                "{} concurrent international calls (limit {})",
+                                concurrent, limit.limit
+                            ),
+                            at: now,
+                        });
+                    }
+                }
+            }
+
+            for violation in &violations {
+                if state.recent_violations.len() >= RECENT_VIOLATIONS {
+                    state.recent_violations.pop_front();
+                }
+                state.recent_violations.push_back(violation.clone());
+            }
+
+            let decision = match violations.iter().map(|v| v.action).max() {
+                Some(FraudAction::Suspend) => {
+                    let reason = violations
+                        .iter()
+                        .filter(|v| v.action == FraudAction::Suspend)
+                        .map(|v| v.detail.clone())
+                        .collect::<Vec<_>>()
+                        .join("; ");
+                    state.suspended = Some(Suspension {
+                        since: now,
+                        reason: reason.clone(),
+                    });
+                    FraudDecision::Block(reason)
+                }
+                action => {
+                    // Count the call only when it goes through
+                    state.prefix_calls.entry(prefix).or_default().push_back(now);
+                    if number.international {
+                        state.active_international.insert(call.call_id.to_string());
+                    }
+                    match action {
+                        Some(FraudAction::Confirm) => FraudDecision::Confirm(violations.clone()),
+                        Some(_) => FraudDecision::Warn(violations.clone()),
+                        None => FraudDecision::Allow,
+                    }
+                }
+            };
+
+            if matches!(decision, FraudDecision::Block(_)) {
+                let suspended = users.values().filter(|s| s.suspended.is_some()).count();
+                gauge!("fraud_suspended_users").set(suspended as f64);
+                counter!("fraud_blocked_calls_total").increment(1);
+            }
+
+            (decision, violations)
+        };
+
+        for violation in violations {
+            self.raise(call, violation);
+        }
+        decision
+    }
+
+    fn raise(&self, call: OutboundCall<'_>, violation: FraudViolation) {
+        warn!(
+            "Fraud rule {} ({}) for {} calling {}: {}",
+            violation.rule.as_str(),
+            violation.action.as_str(),
+            call.username,
+            call.destination,
+            violation.detail
+        );
+        counter!(
+            "fraud_violations_total",
+            "rule" => violation.rule.as_str(),
+            "action" => violation.action.as_str()
+        )
+        .increment(1);
+
+        if let Some(audit) = &self.audit {
+            let severity = match violation.action {
+                FraudAction::Suspend => SecuritySeverity::High,
+                _ => SecuritySeverity::Medium,
+            };
+            audit.log(
+                SecurityEvent::SuspiciousActivity {
+                    username: Some(call.username.to_string()),
+                    ip: "unknown".to_string(),
+                    activity: format!("toll fraud rule {}: {}", violation.rule.as_str(), violation.detail),
+                    risk_score: match violation.action {
+                        FraudAction::Warn => 40,
+                        FraudAction::Confirm => 60,
+                        FraudAction::Suspend => 90,
+                    },
+                },
+                severity,
+            );
+        }
+
+        // No subscribers is fine
+        let _ = self.alerts.send(FraudAlert {
+            username: call.username.to_string(),
+            call_id: call.call_id.to_string(),
+            destination: call.destination.to_string(),
+            violation,
+        });
+    }
+
+    /// Account for a finished call
+    pub fn call_ended(&self, username: &str, call_id: &str, duration: std::time::Duration) {
+        let now = Utc::now();
+        let mut users = self.users.lock().unwrap();
+        if let Some(state) = users.get_mut(username) {
+            state.active_international.remove(call_id);
+            state.roll_day(now);
+            state.seconds_today += duration.as_secs();
+        }
+    }
+
+    /// Whether outbound calling is suspended for a user
+    pub fn is_suspended(&self, username: &str) -> bool {
+        self.users
+            .lock()
+            .unwrap()
+            .get(username)
+            .is_some_and(|s| s.suspended.is_some())
+    }
+
+    /// Lift a suspension and reset the velocity counters
+    ///
+    /// Returns false if the user was not suspended.
+    pub fn clear(&self, username: &str, cleared_by: &str) -> bool {
+        let cleared = {
+            let mut users = self.users.lock().unwrap();
+            let cleared = match users.get_mut(username) {
+                Some(state) if state.suspended.is_some() => {
+                    state.suspended = None;
+                    state.prefix_calls.clear();
+                    true
+                }
+                _ => false,
+            };
+            let suspended = users.values().filter(|s| s.suspended.is_some()).count();
+            gauge!("fraud_suspended_users").set(suspended as f64);
+            cleared
+        };
+
+        if cleared {
+            info!("Outbound calling for {} re-enabled by {}", username, cleared_by);
+            if let Some(audit) = &self.audit {
+                audit.log(
+                    SecurityEvent::AdminAction {
+                        admin_username: cleared_by.to_string(),
+                        ip: "unknown".to_string(),
+                        action: "fraud_clear".to_string(),
+                        target: username.to_string(),
+                    },
+                    SecuritySeverity::Info,
+                );
+            }
+        }
+        cleared
+    }
+
+    /// Current counters for a user
+    pub fn status(&self, username: &str) -> FraudStatus {
+        let now = Utc::now();
+        let mut users = self.users.lock().unwrap();
+        let state = users.entry(username.to_string()).or_default();
+        state.prune(now);
+        state.roll_day(now);
+
+        FraudStatus {
+            username: username.to_string(),
+            suspended: state.suspended.is_some(),
+            suspension: state.suspended.clone(),
+            calls_per_prefix_last_hour: state
+                .prefix_calls
+                .iter()
+                .map(|(prefix, calls)| (prefix.clone(), calls.len()))
+                .collect(),
+            outbound_minutes_today: state.seconds_today as f64 / 60.0,
+            active_international_calls: state.active_international.len(),
+            recent_violations: state.recent_violations.iter().cloned().collect(),
+        }
+    }
+
+    /// Write the counters to `path`
+    pub fn save_to_file(&self, path: &Path) -> std::io::Result<()> {
+        let snapshot = self.users.lock().unwrap().clone();
+        let json = serde_json::to_vec(&snapshot)?;
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        // Write then rename so a crash never leaves a truncated snapshot
+        let tmp = path.with_extension("tmp");
+        std::fs::write(&tmp, json)?;
+        std::fs::rename(tmp, path)
+    }
+
+    /// Load counters written by [`save_to_file`](Self::save_to_file)
+    pub fn load_from_file(&self, path: &Path) -> std::io::Result<()> {
+        let bytes = std::fs::read(path)?;
+        let snapshot: HashMap<String, UserFraudState> = serde_json::from_slice(&bytes)?;
+        let suspended = snapshot.values().filter(|s| s.suspended.is_some()).count();
+        *self.users.lock().unwrap() = snapshot;
+        gauge!("fraud_suspended_users").set(suspended as f64);
+        Ok(())
+    }
+
+    /// Snapshot the counters to `path` every `interval`
+    pub fn spawn_persistence(
+        self: Arc<Self>,
+        path: PathBuf,
+        interval: std::time::Duration,
+    ) -> JoinHandle<()> {
+        tokio::spawn(async move {
+            let mut ticker = tokio::time::interval(interval);
+            ticker.tick().await;
+            loop {
+                ticker.tick().await;
+                if let Err(e) = self.save_to_file(&path) {
+                    error!("Failed to persist fraud counters to {:?}: {}", path, e);
+                }
+            }
+        })
+    }
+}
+
+impl Default for FraudDetector {
+    fn default() -> Self {
+        Self::new(FraudRules::default())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn call<'a>(call_id: &'a str, destination: &'a str) -> OutboundCall<'a> {
+        OutboundCall {
+            realm: None,
+            username: "alice",
+            call_id,
+            destination,
+        }
+    }
+
+    #[test]
+    fn test_premium_rate_burst_suspends_at_threshold() {
+        let detector = FraudDetector::new(FraudRules {
+            calls_per_prefix_per_hour: Some(RuleLimit::new(5, FraudAction::Suspend)),
+            ..Default::default()
+        });
+        let mut alerts = detector.subscribe();
+        let now = Utc::now();
+
+        let ids: Vec<String> = (0..7).map(|i| format!("call-{}", i)).collect();
+        for (i, id) in ids.iter().take(5).enumerate() {
+            let destination = format!("sip:+1900555010{}@example.com", i);
+            let decision = detector.evaluate_at(call(id, &destination), now);
+            assert_eq!(decision, FraudDecision::Allow, "call {} should pass", i);
+        }
+
+        // Sixth call to the same premium-rate prefix engages the block
+        let decision = detector.evaluate_at(call(&ids[5], "sip:+19005550199@example.com"), now);
+        assert!(matches!(decision, FraudDecision::Block(_)));
+        assert!(detector.is_suspended("alice"));
+
+        let alert = alerts.try_recv().unwrap();
+        assert_eq!(alert.violation.rule, FraudRule::PrefixVelocity);
+        assert_eq!(alert.violation.action, FraudAction::Suspend);
+
+        // Any outbound call is blocked while suspended; internal calls are not checked
+        assert!(matches!(
+            detector.evaluate_at(call(&ids[6], "sip:+14155550100@example.com"), now),
+            FraudDecision::Block(_)
+        ));
+        assert_eq!(detector.evaluate_at(call("internal", "sip:1001@example.com"), now), FraudDecision::Allow);
+
+        let status = detector.status("alice");
+        assert!(status.suspended);
+        assert_eq!(status.calls_per_prefix_last_hour.get("190055"), Some(&5));
+
+        assert!(detector.clear("alice", "admin"));
+        assert!(!detector.is_suspended("alice"));
+        assert_eq!(
+            detector.evaluate_at(call("after-clear", "sip:+19005550100@example.com"), now),
+            FraudDecision::Allow
+        );
+    }
+
+    #[test]
+    fn test_velocity_window_expires() {
+        let detector = FraudDetector::new(FraudRules {
+            calls_per_prefix_per_hour: Some(RuleLimit::new(1, FraudAction::Warn)),
+            ..Default::default()
+        });
+        let start = Utc::now();
+
+        assert_eq!(detector.evaluate_at(call("a", "+442079460000"), start), FraudDecision::Allow);
+        assert!(matches!(
+            detector.evaluate_at(call("b", "+442079460001"), start),
+            FraudDecision::Warn(_)
+        ));
+        assert_eq!(
+            detector.evaluate_at(call("c", "+442079460002"), start + Duration::minutes(61)),
+            FraudDecision::Allow
+        );
+    }
+
+    #[test]
+    fn test_country_and_concurrency_rules() {
+        let detector = FraudDetector::new(FraudRules {
+            allowed_countries: Some(vec!["1".to_string(), "44".to_string()]),
+            concurrent_international: Some(RuleLimit::new(1, FraudAction::Suspend)),
+            ..Default::default()
+        });
+
+        assert!(matches!(
+            detector.evaluate(call("a", "011882123456789")),
+            FraudDecision::Confirm(_)
+        ));
+        assert!(matches!(
+            detector.evaluate(call("b", "+442079460000")),
+            FraudDecision::Block(_)
+        ));
+
+        let detector = FraudDetector::default();
+        assert_eq!(detector.evaluate(call("c", "+442079460000")), FraudDecision::Allow);
+        detector.call_ended("alice", "c", std::time::Duration::from_secs(120));
+        let status = detector.status("alice");
+        assert_eq!(status.active_international_calls, 0);
+        assert_eq!(status.outbound_minutes_today, 2.0);
+    }
+
+    #[test]
+    fn test_classify() {
+        let rules = FraudRules::default();
+        assert_eq!(rules.classify("sip:1001@example.com"), None);
+        assert_eq!(
+            rules.classify("sip:4155550100@example.com"),
+            Some(OutboundNumber {
+                e164: "14155550100".to_string(),
+                international: false
+            })
+        );
+        assert!(rules.classify("00442079460000").unwrap().international);
+    }
+}
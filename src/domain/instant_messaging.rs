@@ -54,6 +54,31 @@ pub enum MessageStatus {
     Read,
 }
 
+impl MessageStatus {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            MessageStatus::Pending => "pending",
+            MessageStatus::Delivered => "delivered",
+            MessageStatus::Failed => "failed",
+            MessageStatus::Read => "read",
+        }
+    }
+}
+
+impl std::str::FromStr for MessageStatus {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "pending" => Ok(MessageStatus::Pending),
+            "delivered" => Ok(MessageStatus::Delivered),
+            "failed" => Ok(MessageStatus::Failed),
+            "read" => Ok(MessageStatus::Read),
+            _ => Err(format!("Unknown message status: {}", s)),
+        }
+    }
+}
+
 /// Instant message
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct InstantMessage {
@@ -67,6 +92,12 @@ pub struct InstantMessage {
     pub delivered_at: Option<DateTime<Utc>>,
     pub read_at: Option<DateTime<Utc>>,
     pub group_id: Option<Uuid>,
+    /// Delivery attempts made so far
+    #[serde(default)]
+    pub attempts: u32,
+    /// When delivery of a pending message is retried
+    #[serde(default)]
+    pub next_attempt_at: Option<DateTime<Utc>>,
 }
 
 impl InstantMessage {
@@ -82,6 +113,8 @@ impl InstantMessage {
             delivered_at: None,
             read_at: None,
             group_id: None,
+            attempts: 0,
+            next_attempt_at: None,
         }
     }
 
@@ -116,6 +149,74 @@ impl InstantMessage {
     pub fn is_group_message(&self) -> bool {
         self.group_id.is_some()
     }
+
+    /// Whether a pending message is due for another delivery attempt
+    pub fn is_due(&self, now: DateTime<Utc>) -> bool {
+        self.status == MessageStatus::Pending && self.next_attempt_at.is_none_or(|at| at <= now)
+    }
+}
+
+/// Selects messages of a user's history
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct MessageFilter {
+    /// Only the conversation with this user
+    pub peer: Option<String>,
+    /// Only messages sent before this time (for paging back)
+    pub before: Option<DateTime<Utc>>,
+}
+
+impl MessageFilter {
+    /// Whether `message` is in `user`'s history and selected
+    pub fn matches(&self, user: &str, message: &InstantMessage) -> bool {
+        let peer = if message.from == user {
+            &message.to
+        } else if message.to == user {
+            &message.from
+        } else {
+            return false;
+        };
+        self.peer.as_deref().is_none_or(|p| p == peer)
+            && self.before.is_none_or(|before| message.timestamp < before)
+    }
+}
+
+/// Message repository trait
+///
+/// Users are identified by their SIP username.
+#[cfg_attr(test, mockall::automock)]
+#[async_trait::async_trait]
+pub trait MessageRepository: Send + Sync {
+    /// Store a new message
+    async fn save(&self, message: &InstantMessage) -> Result<(), String>;
+
+    /// Update delivery state of a message
+    async fn update(&self, message: &InstantMessage) -> Result<(), String>;
+
+    /// Get message by ID
+    async fn get_by_id(&self, id: Uuid) -> Result<Option<InstantMessage>, String>;
+
+    /// Pending messages to a user, oldest first
+    async fn list_pending_for(&self, recipient: &str) -> Result<Vec<InstantMessage>, String>;
+
+    /// All pending messages, oldest first
+    async fn list_pending(&self) -> Result<Vec<InstantMessage>, String>;
+
+    /// Messages sent or received by a user, newest first
+    async fn history(
+        &self,
+        user: &str,
+        filter: &MessageFilter,
+        limit: usize,
+    ) -> Result<Vec<InstantMessage>, String>;
+
+    /// Unread messages to a user, by sender
+    async fn unread_counts(&self, user: &str) -> Result<HashMap<String, u64>, String>;
+
+    /// Mark messages from `peer` to a user as read; returns how many were
+    async fn mark_read(&self, user: &str, peer: &str) -> Result<u64, String>;
+
+    /// Delete messages sent or received by a user; returns how many were
+    async fn delete_history(&self, user: &str, filter: &MessageFilter) -> Result<u64, String>;
 }
 
 /// Message group
@@ -14,6 +14,7 @@ pub mod billing;
 pub mod call;
 pub mod call_announcer;
 pub mod call_forwarding;
+pub mod call_history;
 pub mod call_manager;
 pub mod call_parking;
 pub mod call_pickup;
@@ -21,27 +22,44 @@ pub mod call_quality;
 pub mod call_queue;
 pub mod call_queue_engine;
 pub mod call_recording;
+pub mod call_screening;
+pub mod call_survey;
+pub mod campaign;
+pub mod class_of_service;
 pub mod cdr;
+pub mod cdr_retention;
 pub mod conference;
 pub mod conference_manager;
 pub mod conference_recording;
+pub mod device_enrollment;
+pub mod device_provisioning;
+pub mod device_token;
 pub mod dnd;
+pub mod feature_code;
+pub mod fraud_detection;
 pub mod instant_messaging;
 pub mod ip_blacklist;
 pub mod media;
 pub mod music_on_hold;
 pub mod mwi;
 pub mod presence;
+pub mod privacy;
+pub mod queue_callback;
+pub mod queue_reporting;
 pub mod registration;
 pub mod routing;
+pub mod scheduled_call;
 pub mod security;
 pub mod session;
 pub mod shared;
 pub mod sip_trunk;
+pub mod speed_dial;
 pub mod tenant;
+pub mod tenant_branding;
 pub mod user;
 pub mod voicemail;
 pub mod voicemail_ivr;
+pub mod voicemail_list;
 pub mod voicemail_service;
 
 // Re-export commonly used types
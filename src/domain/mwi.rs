@@ -1,3 +1,4 @@
+use crate::domain::voicemail::{VoicemailRepository, VoicemailStatus};
 use chrono::{DateTime, Duration, Utc};
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
@@ -72,6 +73,26 @@ impl MessageSummary {
         }
     }
 
+    /// Build a summary from the mailbox's stored messages
+    ///
+    /// New messages count as new; read and saved messages count as old.
+    pub async fn from_voicemail(
+        repository: &dyn VoicemailRepository,
+        account: MwiAccount,
+        mailbox_id: &str,
+    ) -> Result<Self, String> {
+        let new = repository
+            .count_messages(mailbox_id, Some(VoicemailStatus::New))
+            .await?;
+        let read = repository
+            .count_messages(mailbox_id, Some(VoicemailStatus::Read))
+            .await?;
+        let saved = repository
+            .count_messages(mailbox_id, Some(VoicemailStatus::Saved))
+            .await?;
+        Ok(Self::with_counts(account, new, read + saved, 0, 0))
+    }
+
     pub fn has_new_messages(&self) -> bool {
         self.voice_new > 0 || self.voice_urgent_new > 0
     }
@@ -301,7 +322,10 @@ impl MwiManager {
             if expires_seconds == 0 {
                 // Unsubscribe
                 subscription.terminate();
-                self.send_notification(*sub_id, MessageSummary::new(subscription.account.clone()));
+                let account = subscription.account.clone();
+                // send_notification locks the subscriptions again
+                drop(subscriptions);
+                self.send_notification(*sub_id, MessageSummary::new(account));
             } else {
                 subscription.refresh(expires_seconds);
             }
@@ -316,6 +340,7 @@ impl MwiManager {
         let mut subscriptions = self.subscriptions.lock().unwrap();
 
         if let Some(mut subscription) = subscriptions.remove(sub_id) {
+            drop(subscriptions);
             subscription.terminate();
 
             // Remove from account index
@@ -0,0 +1,538 @@
+//! Erasure of a data subject's personal data
+//!
+//! An erasure request names a subject by number, SIP URI or user. Every
+//! [`SubjectStore`] declares the fields of its records that can identify a
+//! person and finds the records referring to the subject; those older than
+//! the legal retention minimum have the identifying values replaced with a
+//! pseudonym. Pseudonyms are an HMAC of the subject, so repeated requests
+//! for the same person yield the same pseudonym everywhere. Keys, times,
+//! durations and outcomes are left alone: records stay linked to each other
+//! and billing aggregates do not change. Media files can be deleted too.
+//!
+//! The outcome is an [`ErasureReport`] signed for the compliance record.
+
+use crate::domain::call_recording::RecordingMetadata;
+use crate::domain::cdr::CallDetailRecord;
+use crate::domain::instant_messaging::InstantMessage;
+use crate::domain::shared::SecretBox;
+use crate::domain::voicemail::VoicemailMessage;
+use async_trait::async_trait;
+use chrono::{DateTime, Duration, Utc};
+use hmac::{Hmac, Mac};
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+use uuid::Uuid;
+
+type HmacSha256 = Hmac<Sha256>;
+
+/// Erasure settings
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct PrivacyConfig {
+    /// Key pseudonyms are derived with; erasure is refused without one.
+    /// Changing it changes every pseudonym from then on.
+    pub pseudonym_secret: Option<SecretBox>,
+    /// Key erasure reports are signed with; the pseudonym key when unset
+    pub report_secret: Option<SecretBox>,
+    /// Days records must be kept unchanged by law; newer records are left
+    /// as they are and reported as retained
+    pub retention_minimum_days: u32,
+}
+
+impl PrivacyConfig {
+    pub fn validate(&self) -> Result<(), String> {
+        if self.pseudonym_secret.as_ref().is_some_and(|s| s.len() < 16) {
+            return Err("pseudonym_secret must be at least 16 characters".to_string());
+        }
+        if self.report_secret.as_ref().is_some_and(|s| s.len() < 16) {
+            return Err("report_secret must be at least 16 characters".to_string());
+        }
+        if self.retention_minimum_days > 3650 {
+            return Err("retention_minimum_days must be at most 3650".to_string());
+        }
+        Ok(())
+    }
+
+    /// Records made before this are erased
+    pub fn cutoff(&self, now: DateTime<Utc>) -> DateTime<Utc> {
+        now - Duration::days(self.retention_minimum_days as i64)
+    }
+}
+
+/// User part of a SIP or tel URI or name-addr; other input as is
+fn user_part(value: &str) -> &str {
+    let value = match (value.find('<'), value.find('>')) {
+        (Some(start), Some(end)) if start < end => &value[start + 1..end],
+        _ => value,
+    };
+    let value = value.trim();
+    let value = ["sips:", "sip:", "tel:"]
+        .iter()
+        .find_map(|scheme| {
+            value
+                .get(..scheme.len())
+                .filter(|s| s.eq_ignore_ascii_case(scheme))
+                .map(|_| &value[scheme.len()..])
+        })
+        .unwrap_or(value);
+    let value = value.split('@').next().unwrap_or_default();
+    value.split(';').next().unwrap_or_default()
+}
+
+/// What a value identifies: the digits of a number, else the lowercased
+/// user part
+fn subject_key(value: &str) -> Option<String> {
+    let user = user_part(value).trim();
+    let phone_like = user.chars().any(|c| c.is_ascii_digit())
+        && user
+            .chars()
+            .all(|c| c.is_ascii_digit() || matches!(c, '+' | ' ' | '-' | '.' | '(' | ')'));
+    let key = if phone_like {
+        user.chars().filter(|c| c.is_ascii_digit()).collect()
+    } else {
+        user.to_lowercase()
+    };
+    (!key.is_empty() && key != "anonymous").then_some(key)
+}
+
+/// The person an erasure request is about
+#[derive(Debug, Clone)]
+pub struct ErasureSubject {
+    /// Keys the subject is known by; the first names the pseudonym
+    keys: Vec<String>,
+    /// Display names, lowercased
+    names: Vec<String>,
+}
+
+impl ErasureSubject {
+    /// The subject identified by a number, SIP/tel URI or username
+    pub fn new(identifier: &str) -> Result<Self, String> {
+        let key = subject_key(identifier)
+            .ok_or_else(|| format!("Cannot identify a subject by '{}'", identifier))?;
+        Ok(Self {
+            keys: vec![key],
+            names: Vec::new(),
+        })
+    }
+
+    /// Also known by `identifier`, e.g. a user's extension number
+    pub fn with_alias(mut self, identifier: &str) -> Self {
+        if let Some(key) = subject_key(identifier) {
+            if !self.keys.contains(&key) {
+                self.keys.push(key);
+            }
+        }
+        self
+    }
+
+    /// Also named `name`, e.g. as caller ID
+    pub fn with_display_name(mut self, name: &str) -> Self {
+        let name = name.trim().to_lowercase();
+        if !name.is_empty() && !self.names.contains(&name) {
+            self.names.push(name);
+        }
+        self
+    }
+
+    /// Whether `value` refers to the subject
+    pub fn matches(&self, value: &str) -> bool {
+        subject_key(value).is_some_and(|key| self.keys.contains(&key))
+            || self.names.contains(&value.trim().to_lowercase())
+    }
+
+    /// `ILIKE` patterns narrowing a database search down to candidates,
+    /// which are then checked with [`matches`](Self::matches)
+    pub fn search_patterns(&self) -> Vec<String> {
+        self.keys
+            .iter()
+            .chain(&self.names)
+            .map(|term| {
+                let escaped = term
+                    .replace('\\', "\\\\")
+                    .replace('%', "\\%")
+                    .replace('_', "\\_");
+                format!("%{}%", escaped)
+            })
+            .collect()
+    }
+
+    fn key(&self) -> &str {
+        &self.keys[0]
+    }
+}
+
+/// Stable pseudonyms of subjects
+pub struct Pseudonymizer {
+    key: Vec<u8>,
+}
+
+impl Pseudonymizer {
+    pub fn new(secret: &str) -> Self {
+        Self {
+            key: secret.as_bytes().to_vec(),
+        }
+    }
+
+    /// The subject's pseudonym, the same for every request about them
+    pub fn pseudonym(&self, subject: &ErasureSubject) -> String {
+        let mut mac = HmacSha256::new_from_slice(&self.key).expect("HMAC takes any key length");
+        mac.update(subject.key().as_bytes());
+        let digest = hex::encode(mac.finalize().into_bytes());
+        format!("anon-{}", &digest[..16])
+    }
+
+    /// `value` with the subject replaced by their pseudonym; the host of a
+    /// URI is kept, so the record still belongs to its tenant, and the
+    /// display name of a name-addr dropped
+    pub fn replace(&self, subject: &ErasureSubject, value: &str) -> String {
+        let pseudonym = self.pseudonym(subject);
+        let user = user_part(value);
+        if user.is_empty() || user.len() == value.len() {
+            return pseudonym;
+        }
+        // The user part is a slice of `value`
+        let start = user.as_ptr() as usize - value.as_ptr() as usize;
+        let uri_start = value[..start].rfind('<').unwrap_or(0);
+        format!(
+            "{}{}{}",
+            &value[uri_start..start],
+            pseudonym,
+            &value[start + user.len()..]
+        )
+    }
+}
+
+/// A record holding personal data
+///
+/// Only values describing people are listed: keys other records refer to
+/// (IDs, mailbox IDs, Call-IDs) stay as they are.
+pub trait SubjectBearing {
+    /// Fields that can identify a person
+    const SUBJECT_FIELDS: &'static [&'static str];
+
+    /// Identifying values, by field
+    fn subject_values(&self) -> Vec<(&'static str, &str)>;
+
+    /// Identifying values, by field, for overwriting
+    fn subject_values_mut(&mut self) -> Vec<(&'static str, &mut String)>;
+
+    /// Fields whose values refer to `subject`
+    fn fields_referring_to(&self, subject: &ErasureSubject) -> Vec<String> {
+        self.subject_values()
+            .into_iter()
+            .filter(|(_, value)| subject.matches(value))
+            .map(|(field, _)| field.to_string())
+            .collect()
+    }
+
+    /// Replace the values referring to `subject`; true when one was
+    fn pseudonymize(&mut self, subject: &ErasureSubject, pseudonyms: &Pseudonymizer) -> bool {
+        let mut changed = false;
+        for (_, value) in self.subject_values_mut() {
+            if subject.matches(value) {
+                *value = pseudonyms.replace(subject, value);
+                changed = true;
+            }
+        }
+        changed
+    }
+}
+
+impl SubjectBearing for CallDetailRecord {
+    const SUBJECT_FIELDS: &'static [&'static str] = &[
+        "caller_username",
+        "caller_uri",
+        "callee_username",
+        "callee_uri",
+        "dialed_number",
+    ];
+
+    fn subject_values(&self) -> Vec<(&'static str, &str)> {
+        let mut values = vec![
+            ("caller_username", self.caller_username.as_str()),
+            ("caller_uri", self.caller_uri.as_str()),
+            ("callee_username", self.callee_username.as_str()),
+            ("callee_uri", self.callee_uri.as_str()),
+        ];
+        if let Some(dialed) = &self.dialed_number {
+            values.push(("dialed_number", dialed.as_str()));
+        }
+        values
+    }
+
+    fn subject_values_mut(&mut self) -> Vec<(&'static str, &mut String)> {
+        let mut values = vec![
+            ("caller_username", &mut self.caller_username),
+            ("caller_uri", &mut self.caller_uri),
+            ("callee_username", &mut self.callee_username),
+            ("callee_uri", &mut self.callee_uri),
+        ];
+        if let Some(dialed) = &mut self.dialed_number {
+            values.push(("dialed_number", dialed));
+        }
+        values
+    }
+}
+
+impl SubjectBearing for InstantMessage {
+    const SUBJECT_FIELDS: &'static [&'static str] = &["from", "to"];
+
+    fn subject_values(&self) -> Vec<(&'static str, &str)> {
+        vec![("from", self.from.as_str()), ("to", self.to.as_str())]
+    }
+
+    fn subject_values_mut(&mut self) -> Vec<(&'static str, &mut String)> {
+        vec![("from", &mut self.from), ("to", &mut self.to)]
+    }
+}
+
+impl SubjectBearing for VoicemailMessage {
+    const SUBJECT_FIELDS: &'static [&'static str] = &["caller", "caller_name"];
+
+    fn subject_values(&self) -> Vec<(&'static str, &str)> {
+        let mut values = vec![("caller", self.caller.as_str())];
+        if let Some(name) = &self.caller_name {
+            values.push(("caller_name", name.as_str()));
+        }
+        values
+    }
+
+    fn subject_values_mut(&mut self) -> Vec<(&'static str, &mut String)> {
+        let mut values = vec![("caller", &mut self.caller)];
+        if let Some(name) = &mut self.caller_name {
+            values.push(("caller_name", name));
+        }
+        values
+    }
+}
+
+impl SubjectBearing for RecordingMetadata {
+    const SUBJECT_FIELDS: &'static [&'static str] = &["caller", "callee"];
+
+    fn subject_values(&self) -> Vec<(&'static str, &str)> {
+        vec![("caller", self.caller.as_str()), ("callee", self.callee.as_str())]
+    }
+
+    fn subject_values_mut(&mut self) -> Vec<(&'static str, &mut String)> {
+        vec![("caller", &mut self.caller), ("callee", &mut self.callee)]
+    }
+}
+
+/// A record referring to the subject
+#[derive(Debug, Clone, Serialize)]
+pub struct SubjectReference {
+    pub record_id: String,
+    /// When the record was made, for the retention minimum
+    pub at: DateTime<Utc>,
+    /// Fields referring to the subject
+    pub fields: Vec<String>,
+    /// Media file of the record (voicemail audio, a recording)
+    pub media: Option<String>,
+}
+
+impl SubjectReference {
+    /// Reference to `record` when it refers to `subject`
+    pub fn to<T: SubjectBearing>(
+        record: &T,
+        record_id: impl ToString,
+        at: DateTime<Utc>,
+        subject: &ErasureSubject,
+    ) -> Option<Self> {
+        let fields = record.fields_referring_to(subject);
+        (!fields.is_empty()).then(|| Self {
+            record_id: record_id.to_string(),
+            at,
+            fields,
+            media: None,
+        })
+    }
+
+    pub fn with_media(mut self, path: impl Into<String>) -> Self {
+        self.media = Some(path.into());
+        self
+    }
+}
+
+/// Storage holding personal data, registered with the anonymization service
+#[async_trait]
+pub trait SubjectStore: Send + Sync {
+    /// Name of the store in erasure reports
+    fn name(&self) -> &'static str;
+
+    /// Fields of its records that can identify a person
+    fn subject_fields(&self) -> &'static [&'static str];
+
+    /// Records referring to `subject`
+    async fn find_subject(&self, subject: &ErasureSubject) -> Result<Vec<SubjectReference>, String>;
+
+    /// Replace the subject's values in `records` with their pseudonym;
+    /// returns the records changed
+    async fn pseudonymize(
+        &self,
+        subject: &ErasureSubject,
+        pseudonyms: &Pseudonymizer,
+        records: &[SubjectReference],
+    ) -> Result<u64, String>;
+
+    /// Delete the media files of `records`, with the records that would be
+    /// left without them; returns the files deleted
+    async fn erase_media(&self, _records: &[SubjectReference]) -> Result<u64, String> {
+        Ok(0)
+    }
+}
+
+/// What an erasure did in one store
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct StoreErasure {
+    pub store: String,
+    /// Fields the store declares as identifying
+    pub fields: Vec<String>,
+    /// Records referring to the subject
+    pub found: u64,
+    pub pseudonymized: u64,
+    /// Records newer than the retention minimum, left unchanged
+    pub retained: u64,
+    pub media_deleted: u64,
+    /// IDs of the records erased (or that would be, in a dry run)
+    pub record_ids: Vec<String>,
+}
+
+/// Signed record of an erasure
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ErasureReport {
+    pub id: Uuid,
+    /// Pseudonym of the subject; the request itself is not recorded
+    pub subject: String,
+    pub requested_by: String,
+    /// Nothing was changed; counts are what would have been
+    pub dry_run: bool,
+    pub media_deleted: bool,
+    /// Records made before this were erased
+    pub cutoff: DateTime<Utc>,
+    pub stores: Vec<StoreErasure>,
+    pub started_at: DateTime<Utc>,
+    pub finished_at: DateTime<Utc>,
+    /// HMAC-SHA256 (hex) of the report without its signature
+    #[serde(default)]
+    pub signature: String,
+}
+
+impl ErasureReport {
+    fn digest(&self, secret: &str) -> HmacSha256 {
+        let unsigned = ErasureReport {
+            signature: String::new(),
+            ..self.clone()
+        };
+        let mut mac =
+            HmacSha256::new_from_slice(secret.as_bytes()).expect("HMAC takes any key length");
+        mac.update(&serde_json::to_vec(&unsigned).unwrap_or_default());
+        mac
+    }
+
+    pub fn sign(&mut self, secret: &str) {
+        self.signature = hex::encode(self.digest(secret).finalize().into_bytes());
+    }
+
+    /// Whether the report is unchanged since it was signed with `secret`
+    pub fn verify(&self, secret: &str) -> bool {
+        hex::decode(&self.signature)
+            .is_ok_and(|signature| self.digest(secret).verify_slice(&signature).is_ok())
+    }
+
+    /// Records pseudonymized over all stores
+    pub fn pseudonymized(&self) -> u64 {
+        self.stores.iter().map(|store| store.pseudonymized).sum()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::domain::cdr::CallDirection;
+
+    #[test]
+    fn test_subject_matching() {
+        let subject = ErasureSubject::new("sip:alice@example.com")
+            .unwrap()
+            .with_alias("+49 30 1234")
+            .with_display_name("Alice Smith");
+
+        assert!(subject.matches("alice"));
+        assert!(subject.matches("\"Alice\" <sip:Alice@other.example.com;transport=tcp>"));
+        assert!(subject.matches("tel:+49-30-1234"));
+        assert!(subject.matches("sip:49301234@trunk.example.net"));
+        assert!(subject.matches("alice smith"));
+        assert!(!subject.matches("sip:bob@example.com"));
+        assert!(!subject.matches("sip:alice2@example.com"));
+        assert!(ErasureSubject::new("sip:@example.com").is_err());
+        assert!(ErasureSubject::new("anonymous").is_err());
+    }
+
+    #[test]
+    fn test_pseudonyms_are_stable() {
+        let pseudonyms = Pseudonymizer::new("a very secret pseudonym key");
+        let alice = ErasureSubject::new("alice").unwrap();
+        let again = ErasureSubject::new("<sip:ALICE@example.com>").unwrap();
+        assert_eq!(pseudonyms.pseudonym(&alice), pseudonyms.pseudonym(&again));
+        assert_ne!(
+            pseudonyms.pseudonym(&alice),
+            Pseudonymizer::new("another secret pseudonym key").pseudonym(&alice)
+        );
+
+        let pseudonym = pseudonyms.pseudonym(&alice);
+        assert_eq!(
+            pseudonyms.replace(&alice, "sip:alice@example.com"),
+            format!("sip:{}@example.com", pseudonym)
+        );
+        assert_eq!(pseudonyms.replace(&alice, "alice"), pseudonym);
+        assert_eq!(
+            pseudonyms.replace(&alice, "\"Alice\" <sip:alice@example.com>;tag=1"),
+            format!("<sip:{}@example.com>;tag=1", pseudonym)
+        );
+
+        let mut cdr = CallDetailRecord::new(
+            "call-1".to_string(),
+            "alice".to_string(),
+            "sip:alice@example.com".to_string(),
+            "192.0.2.1".to_string(),
+            "bob".to_string(),
+            "sip:bob@example.com".to_string(),
+            CallDirection::Internal,
+        );
+        assert_eq!(
+            cdr.fields_referring_to(&alice),
+            ["caller_username", "caller_uri"]
+        );
+        assert!(cdr.pseudonymize(&alice, &pseudonyms));
+        assert_eq!(cdr.caller_username, pseudonym);
+        assert_eq!(cdr.callee_username, "bob");
+        assert!(cdr.fields_referring_to(&alice).is_empty());
+    }
+
+    #[test]
+    fn test_report_signature() {
+        let now = Utc::now();
+        let mut report = ErasureReport {
+            id: Uuid::new_v4(),
+            subject: "anon-0123456789abcdef".to_string(),
+            requested_by: "admin".to_string(),
+            dry_run: false,
+            media_deleted: false,
+            cutoff: now,
+            stores: vec![StoreErasure {
+                store: "cdrs".to_string(),
+                pseudonymized: 2,
+                ..Default::default()
+            }],
+            started_at: now,
+            finished_at: now,
+            signature: String::new(),
+        };
+        report.sign("report signing secret");
+        assert!(report.verify("report signing secret"));
+        assert!(!report.verify("another signing secret"));
+
+        report.stores[0].pseudonymized = 1;
+        assert!(!report.verify("report signing secret"));
+    }
+}
@@ -0,0 +1,230 @@
+//! Queue callback offer
+//!
+//! After a waiting caller presses the queue's callback digit, the caller is
+//! asked to confirm the number to call back: the caller ID by default, or a
+//! number entered with DTMF and read back before it is accepted.
+
+/// Longest callback number accepted from DTMF entry
+pub const MAX_CALLBACK_NUMBER_LEN: usize = 20;
+
+/// Shortest callback number accepted from DTMF entry
+pub const MIN_CALLBACK_NUMBER_LEN: usize = 3;
+
+/// Callback prompt types
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CallbackPrompt {
+    /// Press 1 to be called back at <number>, 2 to enter another number
+    ConfirmNumber(String),
+    /// Enter the number followed by #
+    EnterNumber,
+    /// You entered <number>; press 1 to confirm, 2 to re-enter
+    ReadBack(String),
+    /// Number too short or too long
+    InvalidNumber,
+    /// We will call you back; your place in line is kept
+    Confirmed,
+    /// Returning to the queue
+    Cancelled,
+    /// This is your callback (played to the callee before the agent)
+    ThisIsYourCallback,
+}
+
+impl CallbackPrompt {
+    /// Get prompt audio file ID
+    pub fn audio_id(&self) -> &'static str {
+        match self {
+            Self::ConfirmNumber(_) => "queue_callback_confirm",
+            Self::EnterNumber => "queue_callback_enter_number",
+            Self::ReadBack(_) => "queue_callback_readback",
+            Self::InvalidNumber => "queue_callback_invalid_number",
+            Self::Confirmed => "queue_callback_confirmed",
+            Self::Cancelled => "queue_callback_cancelled",
+            Self::ThisIsYourCallback => "queue_callback_intro",
+        }
+    }
+
+    /// Number to read back digit by digit after the prompt
+    pub fn number(&self) -> Option<&str> {
+        match self {
+            Self::ConfirmNumber(number) | Self::ReadBack(number) => Some(number),
+            _ => None,
+        }
+    }
+}
+
+/// Callback offer state
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CallbackOfferState {
+    /// Waiting for the caller to accept the caller ID number
+    ConfirmingCallerId(String),
+    /// Collecting digits of another number
+    EnteringNumber(String),
+    /// Waiting for the caller to confirm the entered number
+    ReadingBack(String),
+    /// Caller accepted the number
+    Accepted(String),
+    /// Caller went back to waiting in the queue
+    Cancelled,
+}
+
+/// Collects the callback number from a waiting caller
+#[derive(Debug, Clone)]
+pub struct CallbackOffer {
+    state: CallbackOfferState,
+}
+
+impl CallbackOffer {
+    /// Start the offer; returns the first prompt to play
+    pub fn start(caller: &str) -> (Self, CallbackPrompt) {
+        match caller_number(caller) {
+            Some(number) => (
+                Self {
+                    state: CallbackOfferState::ConfirmingCallerId(number.clone()),
+                },
+                CallbackPrompt::ConfirmNumber(number),
+            ),
+            None => (
+                Self {
+                    state: CallbackOfferState::EnteringNumber(String::new()),
+                },
+                CallbackPrompt::EnterNumber,
+            ),
+        }
+    }
+
+    pub fn state(&self) -> &CallbackOfferState {
+        &self.state
+    }
+
+    /// Number accepted by the caller, once the offer is complete
+    pub fn accepted_number(&self) -> Option<&str> {
+        match &self.state {
+            CallbackOfferState::Accepted(number) => Some(number),
+            _ => None,
+        }
+    }
+
+    pub fn is_finished(&self) -> bool {
+        matches!(
+            self.state,
+            CallbackOfferState::Accepted(_) | CallbackOfferState::Cancelled
+        )
+    }
+
+    /// Handle a DTMF digit; returns the prompt to play, if any
+    ///
+    /// `*` abandons the offer and leaves the caller waiting in the queue.
+    pub fn handle_digit(&mut self, digit: char) -> Option<CallbackPrompt> {
+        if digit == '*' && !self.is_finished() {
+            self.state = CallbackOfferState::Cancelled;
+            return Some(CallbackPrompt::Cancelled);
+        }
+
+        match &mut self.state {
+            CallbackOfferState::ConfirmingCallerId(number)
+            | CallbackOfferState::ReadingBack(number) => match digit {
+                '1' => {
+                    self.state = CallbackOfferState::Accepted(number.clone());
+                    Some(CallbackPrompt::Confirmed)
+                }
+                '2' => {
+                    self.state = CallbackOfferState::EnteringNumber(String::new());
+                    Some(CallbackPrompt::EnterNumber)
+                }
+                _ => None,
+            },
+            CallbackOfferState::EnteringNumber(digits) => match digit {
+                '#' => {
+                    if (MIN_CALLBACK_NUMBER_LEN..=MAX_CALLBACK_NUMBER_LEN).contains(&digits.len()) {
+                        let number = std::mem::take(digits);
+                        self.state = CallbackOfferState::ReadingBack(number.clone());
+                        Some(CallbackPrompt::ReadBack(number))
+                    } else {
+                        digits.clear();
+                        Some(CallbackPrompt::InvalidNumber)
+                    }
+                }
+                d if d.is_ascii_digit() => {
+                    if digits.len() < MAX_CALLBACK_NUMBER_LEN {
+                        digits.push(d);
+                    }
+                    None
+                }
+                _ => None,
+            },
+            CallbackOfferState::Accepted(_) | CallbackOfferState::Cancelled => None,
+        }
+    }
+}
+
+/// Dialable number of a caller URI (`sip:+15551234567@host` -> `+15551234567`)
+pub fn caller_number(caller: &str) -> Option<String> {
+    let user = caller
+        .trim()
+        .trim_start_matches("sips:")
+        .trim_start_matches("sip:")
+        .trim_start_matches("tel:");
+    let user = user.split(['@', ';']).next().unwrap_or_default();
+    let digits = user.strip_prefix('+').unwrap_or(user);
+    if digits.len() < MIN_CALLBACK_NUMBER_LEN || !digits.chars().all(|c| c.is_ascii_digit()) {
+        return None;
+    }
+    Some(user.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_accept_caller_id() {
+        let (mut offer, prompt) = CallbackOffer::start("sip:+15551234567@example.com");
+        assert_eq!(
+            prompt,
+            CallbackPrompt::ConfirmNumber("+15551234567".to_string())
+        );
+
+        assert_eq!(offer.handle_digit('1'), Some(CallbackPrompt::Confirmed));
+        assert_eq!(offer.accepted_number(), Some("+15551234567"));
+        assert!(offer.is_finished());
+    }
+
+    #[test]
+    fn test_enter_other_number_with_readback() {
+        let (mut offer, _) = CallbackOffer::start("sip:15551234567@example.com");
+        assert_eq!(offer.handle_digit('2'), Some(CallbackPrompt::EnterNumber));
+
+        // Too short: entry starts over
+        for digit in "12#".chars() {
+            offer.handle_digit(digit);
+        }
+        assert_eq!(
+            offer.state(),
+            &CallbackOfferState::EnteringNumber(String::new())
+        );
+
+        for digit in "5550100".chars() {
+            assert_eq!(offer.handle_digit(digit), None);
+        }
+        assert_eq!(
+            offer.handle_digit('#'),
+            Some(CallbackPrompt::ReadBack("5550100".to_string()))
+        );
+        // Re-enter, then confirm
+        assert_eq!(offer.handle_digit('2'), Some(CallbackPrompt::EnterNumber));
+        for digit in "5550199#".chars() {
+            offer.handle_digit(digit);
+        }
+        assert_eq!(offer.handle_digit('1'), Some(CallbackPrompt::Confirmed));
+        assert_eq!(offer.accepted_number(), Some("5550199"));
+    }
+
+    #[test]
+    fn test_anonymous_caller_and_cancel() {
+        let (mut offer, prompt) = CallbackOffer::start("sip:anonymous@anonymous.invalid");
+        assert_eq!(prompt, CallbackPrompt::EnterNumber);
+        assert_eq!(offer.handle_digit('*'), Some(CallbackPrompt::Cancelled));
+        assert!(offer.is_finished());
+        assert_eq!(offer.accepted_number(), None);
+    }
+}
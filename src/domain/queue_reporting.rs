@@ -0,0 +1,644 @@
+//! Queue and agent reporting
+//!
+//! The call queue engine emits a [`QueueEvent`] for every step of a queued
+//! call (enqueued, offered to an agent, answered, abandoned, overflowed,
+//! completed). Events are persisted through a [`QueueEventRepository`] and
+//! aggregated on demand into per-queue and per-agent interval reports.
+//!
+//! Queue figures are attributed to the interval in which the call entered
+//! the queue, even when it was answered or abandoned after the interval
+//! ended. Agent talk and wrap-up time is split across the intervals it
+//! overlaps.
+
+use chrono::{DateTime, Duration as ChronoDuration, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::mpsc;
+use tokio::task::JoinHandle;
+use tracing::error;
+use uuid::Uuid;
+
+/// How far past the end of a report window events are still read, so calls
+/// entering the queue near the end of the window get their outcome
+pub const REPORT_LOOKAHEAD: ChronoDuration = ChronoDuration::hours(4);
+
+/// Upper bound on the number of intervals in one report
+pub const MAX_REPORT_INTERVALS: i64 = 2000;
+
+/// Queue event type
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum QueueEventType {
+    /// Call entered the queue
+    Enqueued,
+    /// Call was presented to an agent
+    Offered,
+    /// Agent answered the call
+    Answered,
+    /// Caller hung up while waiting
+    Abandoned,
+    /// Call left the queue through the overflow action
+    Overflowed,
+    /// Call finished, including the agent's wrap-up time
+    Completed,
+}
+
+impl QueueEventType {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Enqueued => "enqueued",
+            Self::Offered => "offered",
+            Self::Answered => "answered",
+            Self::Abandoned => "abandoned",
+            Self::Overflowed => "overflowed",
+            Self::Completed => "completed",
+        }
+    }
+}
+
+impl std::str::FromStr for QueueEventType {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "enqueued" => Ok(Self::Enqueued),
+            "offered" => Ok(Self::Offered),
+            "answered" => Ok(Self::Answered),
+            "abandoned" => Ok(Self::Abandoned),
+            "overflowed" => Ok(Self::Overflowed),
+            "completed" => Ok(Self::Completed),
+            _ => Err(format!("Unknown queue event type: {}", s)),
+        }
+    }
+}
+
+/// A single step of a queued call
+///
+/// `wait_secs` is set on answered, abandoned and overflowed events.
+/// Completed events are stamped when the talk ends and carry `talk_secs`
+/// and `wrap_up_secs`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct QueueEvent {
+    pub id: Uuid,
+    pub queue_id: Uuid,
+    pub call_id: String,
+    /// User ID of the agent, for offered/answered/completed events
+    pub agent_id: Option<i32>,
+    pub event_type: QueueEventType,
+    pub occurred_at: DateTime<Utc>,
+    pub wait_secs: i64,
+    pub talk_secs: i64,
+    pub wrap_up_secs: i64,
+}
+
+impl QueueEvent {
+    pub fn new(queue_id: Uuid, call_id: impl Into<String>, event_type: QueueEventType) -> Self {
+        Self {
+            id: Uuid::new_v4(),
+            queue_id,
+            call_id: call_id.into(),
+            agent_id: None,
+            event_type,
+            occurred_at: Utc::now(),
+            wait_secs: 0,
+            talk_secs: 0,
+            wrap_up_secs: 0,
+        }
+    }
+
+    pub fn with_agent(mut self, agent_id: i32) -> Self {
+        self.agent_id = Some(agent_id);
+        self
+    }
+
+    pub fn with_occurred_at(mut self, occurred_at: DateTime<Utc>) -> Self {
+        self.occurred_at = occurred_at;
+        self
+    }
+
+    pub fn with_wait(mut self, wait: Duration) -> Self {
+        self.wait_secs = wait.as_secs() as i64;
+        self
+    }
+
+    pub fn with_talk(mut self, talk: Duration) -> Self {
+        self.talk_secs = talk.as_secs() as i64;
+        self
+    }
+
+    pub fn with_wrap_up(mut self, wrap_up: Duration) -> Self {
+        self.wrap_up_secs = wrap_up.as_secs() as i64;
+        self
+    }
+}
+
+/// Repository trait for queue event persistence
+#[cfg_attr(test, mockall::automock)]
+#[async_trait::async_trait]
+pub trait QueueEventRepository: Send + Sync {
+    /// Store an event
+    async fn record(&self, event: &QueueEvent) -> Result<(), String>;
+
+    /// Events of a queue with `from <= occurred_at < to`, oldest first
+    async fn list_for_queue(
+        &self,
+        queue_id: Uuid,
+        from: DateTime<Utc>,
+        to: DateTime<Utc>,
+    ) -> Result<Vec<QueueEvent>, String>;
+
+    /// Events handled by an agent with `from <= occurred_at < to`, oldest first
+    async fn list_for_agent(
+        &self,
+        agent_id: i32,
+        from: DateTime<Utc>,
+        to: DateTime<Utc>,
+    ) -> Result<Vec<QueueEvent>, String>;
+}
+
+/// Persist events received from the queue engine
+pub fn spawn_queue_event_recorder(
+    repository: Arc<dyn QueueEventRepository>,
+    mut events: mpsc::UnboundedReceiver<QueueEvent>,
+) -> JoinHandle<()> {
+    tokio::spawn(async move {
+        while let Some(event) = events.recv().await {
+            if let Err(e) = repository.record(&event).await {
+                error!(
+                    "Failed to record {} event for call {}: {}",
+                    event.event_type.as_str(),
+                    event.call_id,
+                    e
+                );
+            }
+        }
+    })
+}
+
+/// Parse a report interval: `15m`, `1h`, `1d` or plain seconds
+pub fn parse_interval(value: &str) -> Option<ChronoDuration> {
+    let value = value.trim();
+    let (number, unit) = match value.char_indices().last()? {
+        (i, c) if c.is_ascii_alphabetic() => (&value[..i], c),
+        _ => (value, 's'),
+    };
+    let number: i64 = number.parse().ok().filter(|n| *n > 0)?;
+    match unit {
+        's' => Some(ChronoDuration::seconds(number)),
+        'm' => Some(ChronoDuration::minutes(number)),
+        'h' => Some(ChronoDuration::hours(number)),
+        'd' => Some(ChronoDuration::days(number)),
+        _ => None,
+    }
+}
+
+/// `[start, end)` bounds of each interval in a report window
+fn interval_bounds(
+    from: DateTime<Utc>,
+    to: DateTime<Utc>,
+    interval: ChronoDuration,
+) -> Vec<(DateTime<Utc>, DateTime<Utc>)> {
+    let mut bounds = Vec::new();
+    let mut start = from;
+    while start < to {
+        let end = (start + interval).min(to);
+        bounds.push((start, end));
+        start = end;
+    }
+    bounds
+}
+
+fn percent(part: u64, whole: u64) -> f64 {
+    if whole == 0 {
+        0.0
+    } else {
+        part as f64 / whole as f64 * 100.0
+    }
+}
+
+/// Seconds of `[start, end)` falling inside `[from, to)`
+fn overlap_secs(
+    start: DateTime<Utc>,
+    end: DateTime<Utc>,
+    from: DateTime<Utc>,
+    to: DateTime<Utc>,
+) -> i64 {
+    (end.min(to) - start.max(from)).num_seconds().max(0)
+}
+
+/// Queue figures for one interval
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct QueueIntervalStats {
+    pub start: DateTime<Utc>,
+    pub end: DateTime<Utc>,
+    /// Calls that entered the queue
+    pub offered: u64,
+    pub answered: u64,
+    pub answered_within_threshold: u64,
+    pub abandoned: u64,
+    pub overflowed: u64,
+    /// % of answered calls answered within the service level threshold
+    pub service_level: f64,
+    /// % of offered calls abandoned by the caller
+    pub abandonment_rate: f64,
+    /// Average wait of answered calls
+    pub avg_wait_secs: f64,
+    /// Longest wait of an answered or abandoned call
+    pub max_wait_secs: i64,
+    #[serde(skip)]
+    total_answered_wait_secs: i64,
+}
+
+impl QueueIntervalStats {
+    fn empty(start: DateTime<Utc>, end: DateTime<Utc>) -> Self {
+        Self {
+            start,
+            end,
+            offered: 0,
+            answered: 0,
+            answered_within_threshold: 0,
+            abandoned: 0,
+            overflowed: 0,
+            service_level: 0.0,
+            abandonment_rate: 0.0,
+            avg_wait_secs: 0.0,
+            max_wait_secs: 0,
+            total_answered_wait_secs: 0,
+        }
+    }
+
+    fn add(&mut self, call: &QueuedCallOutcome, threshold_secs: i64) {
+        self.offered += 1;
+        match call.outcome {
+            Some(QueueEventType::Answered) => {
+                self.answered += 1;
+                if call.wait_secs <= threshold_secs {
+                    self.answered_within_threshold += 1;
+                }
+                self.total_answered_wait_secs += call.wait_secs;
+                self.max_wait_secs = self.max_wait_secs.max(call.wait_secs);
+            }
+            Some(QueueEventType::Abandoned) => {
+                self.abandoned += 1;
+                self.max_wait_secs = self.max_wait_secs.max(call.wait_secs);
+            }
+            Some(QueueEventType::Overflowed) => self.overflowed += 1,
+            _ => {}
+        }
+    }
+
+    fn finish(&mut self) {
+        self.service_level = percent(self.answered_within_threshold, self.answered);
+        self.abandonment_rate = percent(self.abandoned, self.offered);
+        if self.answered > 0 {
+            self.avg_wait_secs = self.total_answered_wait_secs as f64 / self.answered as f64;
+        }
+    }
+}
+
+/// Per-queue report
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct QueueReport {
+    pub queue_id: Uuid,
+    pub from: DateTime<Utc>,
+    pub to: DateTime<Utc>,
+    pub interval_secs: i64,
+    pub service_level_threshold_secs: i64,
+    pub totals: QueueIntervalStats,
+    pub intervals: Vec<QueueIntervalStats>,
+}
+
+/// Where a call entered the queue and how it left
+struct QueuedCallOutcome {
+    arrived_at: Option<DateTime<Utc>>,
+    outcome: Option<QueueEventType>,
+    wait_secs: i64,
+}
+
+/// Aggregate queue events into a report
+///
+/// Pass events from `from` to at least `to + REPORT_LOOKAHEAD` so calls
+/// entering the queue just before `to` have their outcome.
+pub fn build_queue_report(
+    queue_id: Uuid,
+    service_level_threshold: Duration,
+    events: &[QueueEvent],
+    from: DateTime<Utc>,
+    to: DateTime<Utc>,
+    interval: ChronoDuration,
+) -> QueueReport {
+    let threshold_secs = service_level_threshold.as_secs() as i64;
+
+    let mut calls: HashMap<&str, QueuedCallOutcome> = HashMap::new();
+    for event in events.iter().filter(|e| e.queue_id == queue_id) {
+        let call = calls.entry(event.call_id.as_str()).or_insert(QueuedCallOutcome {
+            arrived_at: None,
+            outcome: None,
+            wait_secs: 0,
+        });
+        match event.event_type {
+            QueueEventType::Enqueued => call.arrived_at = Some(event.occurred_at),
+            QueueEventType::Answered | QueueEventType::Abandoned | QueueEventType::Overflowed => {
+                // A call rejected because the queue was full never enqueued
+                if call.arrived_at.is_none() && event.event_type == QueueEventType::Overflowed {
+                    call.arrived_at = Some(event.occurred_at);
+                }
+                if call.outcome.is_none() {
+                    call.outcome = Some(event.event_type);
+                    call.wait_secs = event.wait_secs;
+                }
+            }
+            QueueEventType::Offered | QueueEventType::Completed => {}
+        }
+    }
+
+    let bounds = interval_bounds(from, to, interval);
+    let mut intervals: Vec<QueueIntervalStats> = bounds
+        .iter()
+        .map(|(start, end)| QueueIntervalStats::empty(*start, *end))
+        .collect();
+    let mut totals = QueueIntervalStats::empty(from, to);
+
+    for call in calls.values() {
+        let arrived_at = match call.arrived_at {
+            Some(at) if at >= from && at < to => at,
+            _ => continue,
+        };
+        let index = ((arrived_at - from).num_seconds() / interval.num_seconds()) as usize;
+        if let Some(stats) = intervals.get_mut(index) {
+            stats.add(call, threshold_secs);
+        }
+        totals.add(call, threshold_secs);
+    }
+
+    intervals.iter_mut().for_each(QueueIntervalStats::finish);
+    totals.finish();
+
+    QueueReport {
+        queue_id,
+        from,
+        to,
+        interval_secs: interval.num_seconds(),
+        service_level_threshold_secs: threshold_secs,
+        totals,
+        intervals,
+    }
+}
+
+/// Agent figures for one interval
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct AgentIntervalStats {
+    pub start: DateTime<Utc>,
+    pub end: DateTime<Utc>,
+    /// Calls presented to the agent
+    pub offered: u64,
+    pub answered: u64,
+    /// Offered calls the agent did not answer
+    pub missed: u64,
+    pub talk_secs: i64,
+    pub wrap_up_secs: i64,
+    /// % of the interval spent talking or in wrap-up
+    pub occupancy: f64,
+}
+
+impl AgentIntervalStats {
+    fn empty(start: DateTime<Utc>, end: DateTime<Utc>) -> Self {
+        Self {
+            start,
+            end,
+            offered: 0,
+            answered: 0,
+            missed: 0,
+            talk_secs: 0,
+            wrap_up_secs: 0,
+            occupancy: 0.0,
+        }
+    }
+
+    fn add(&mut self, event: &QueueEvent) {
+        let in_interval = event.occurred_at >= self.start && event.occurred_at < self.end;
+        match event.event_type {
+            QueueEventType::Offered if in_interval => self.offered += 1,
+            QueueEventType::Answered if in_interval => self.answered += 1,
+            QueueEventType::Completed => {
+                let talk_start = event.occurred_at - ChronoDuration::seconds(event.talk_secs);
+                let wrap_up_end = event.occurred_at + ChronoDuration::seconds(event.wrap_up_secs);
+                self.talk_secs += overlap_secs(talk_start, event.occurred_at, self.start, self.end);
+                self.wrap_up_secs += overlap_secs(event.occurred_at, wrap_up_end, self.start, self.end);
+            }
+            _ => {}
+        }
+    }
+
+    fn finish(&mut self) {
+        self.missed = self.offered.saturating_sub(self.answered);
+        let length = (self.end - self.start).num_seconds();
+        if length > 0 {
+            self.occupancy = (self.talk_secs + self.wrap_up_secs) as f64 / length as f64 * 100.0;
+        }
+    }
+}
+
+/// Per-agent report
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AgentReport {
+    pub agent_id: i32,
+    pub from: DateTime<Utc>,
+    pub to: DateTime<Utc>,
+    pub interval_secs: i64,
+    pub totals: AgentIntervalStats,
+    pub intervals: Vec<AgentIntervalStats>,
+}
+
+/// Aggregate an agent's events into a report
+///
+/// Pass events from `from - REPORT_LOOKAHEAD` to `to + REPORT_LOOKAHEAD`
+/// so talk overlapping either edge of the window is counted.
+pub fn build_agent_report(
+    agent_id: i32,
+    events: &[QueueEvent],
+    from: DateTime<Utc>,
+    to: DateTime<Utc>,
+    interval: ChronoDuration,
+) -> AgentReport {
+    let mut intervals: Vec<AgentIntervalStats> = interval_bounds(from, to, interval)
+        .into_iter()
+        .map(|(start, end)| AgentIntervalStats::empty(start, end))
+        .collect();
+    let mut totals = AgentIntervalStats::empty(from, to);
+
+    for event in events.iter().filter(|e| e.agent_id == Some(agent_id)) {
+        for stats in intervals.iter_mut() {
+            stats.add(event);
+        }
+        totals.add(event);
+    }
+
+    intervals.iter_mut().for_each(AgentIntervalStats::finish);
+    totals.finish();
+
+    AgentReport {
+        agent_id,
+        from,
+        to,
+        interval_secs: interval.num_seconds(),
+        totals,
+        intervals,
+    }
+}
+
+/// Agents of a queue by state
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AgentStateCounts {
+    pub available: usize,
+    pub busy: usize,
+    pub after_call_work: usize,
+    pub paused: usize,
+    pub logged_out: usize,
+}
+
+/// Real-time wallboard view of a queue
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct QueueSnapshot {
+    pub queue_id: Uuid,
+    pub name: String,
+    pub calls_waiting: usize,
+    pub calls_active: usize,
+    pub longest_wait_secs: u64,
+    pub agents: AgentStateCounts,
+    pub taken_at: DateTime<Utc>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    fn at(hour: u32, min: u32, sec: u32) -> DateTime<Utc> {
+        Utc.with_ymd_and_hms(2025, 11, 9, hour, min, sec).unwrap()
+    }
+
+    fn event(
+        queue_id: Uuid,
+        call_id: &str,
+        event_type: QueueEventType,
+        occurred_at: DateTime<Utc>,
+    ) -> QueueEvent {
+        QueueEvent::new(queue_id, call_id, event_type).with_occurred_at(occurred_at)
+    }
+
+    #[test]
+    fn test_parse_interval() {
+        assert_eq!(parse_interval("15m"), Some(ChronoDuration::minutes(15)));
+        assert_eq!(parse_interval("1h"), Some(ChronoDuration::hours(1)));
+        assert_eq!(parse_interval("1d"), Some(ChronoDuration::days(1)));
+        assert_eq!(parse_interval("900"), Some(ChronoDuration::seconds(900)));
+        assert_eq!(parse_interval("0m"), None);
+        assert_eq!(parse_interval("2w"), None);
+    }
+
+    #[test]
+    fn test_queue_report_service_level_and_abandonment() {
+        let queue_id = Uuid::new_v4();
+        let secs = |s| Duration::from_secs(s);
+        let events = vec![
+            // Answered within the 20s threshold
+            event(queue_id, "a", QueueEventType::Enqueued, at(10, 0, 5)),
+            event(queue_id, "a", QueueEventType::Answered, at(10, 0, 15)).with_agent(1).with_wait(secs(10)),
+            // Answered late
+            event(queue_id, "b", QueueEventType::Enqueued, at(10, 1, 0)),
+            event(queue_id, "b", QueueEventType::Answered, at(10, 1, 45)).with_agent(2).with_wait(secs(45)),
+            // Abandoned
+            event(queue_id, "c", QueueEventType::Enqueued, at(10, 2, 0)),
+            event(queue_id, "c", QueueEventType::Abandoned, at(10, 2, 30)).with_wait(secs(30)),
+            // Enqueued in the first interval, answered in the second
+            event(queue_id, "d", QueueEventType::Enqueued, at(10, 14, 50)),
+            event(queue_id, "d", QueueEventType::Answered, at(10, 15, 5)).with_agent(1).with_wait(secs(15)),
+            // Second interval
+            event(queue_id, "e", QueueEventType::Enqueued, at(10, 20, 0)),
+            event(queue_id, "e", QueueEventType::Answered, at(10, 20, 5)).with_agent(2).with_wait(secs(5)),
+            // Rejected with the queue full
+            event(queue_id, "f", QueueEventType::Overflowed, at(10, 25, 0)),
+            // Enqueued before the window: not counted
+            event(queue_id, "g", QueueEventType::Enqueued, at(9, 59, 50)),
+            event(queue_id, "g", QueueEventType::Answered, at(10, 0, 20)).with_agent(1).with_wait(secs(30)),
+        ];
+
+        let report = build_queue_report(
+            queue_id,
+            Duration::from_secs(20),
+            &events,
+            at(10, 0, 0),
+            at(10, 30, 0),
+            ChronoDuration::minutes(15),
+        );
+
+        assert_eq!(report.intervals.len(), 2);
+
+        let first = &report.intervals[0];
+        assert_eq!(first.offered, 4);
+        assert_eq!(first.answered, 3);
+        assert_eq!(first.answered_within_threshold, 2);
+        assert_eq!(first.abandoned, 1);
+        assert!((first.service_level - 200.0 / 3.0).abs() < 1e-9);
+        assert!((first.abandonment_rate - 25.0).abs() < 1e-9);
+        assert_eq!(first.max_wait_secs, 45);
+
+        let second = &report.intervals[1];
+        assert_eq!(second.offered, 2);
+        assert_eq!(second.answered, 1);
+        assert_eq!(second.overflowed, 1);
+        assert_eq!(second.abandoned, 0);
+        assert!((second.service_level - 100.0).abs() < 1e-9);
+
+        assert_eq!(report.totals.offered, 6);
+        assert_eq!(report.totals.answered, 4);
+        assert!((report.totals.service_level - 75.0).abs() < 1e-9);
+        assert!((report.totals.abandonment_rate - 100.0 / 6.0).abs() < 1e-9);
+        assert!((report.totals.avg_wait_secs - 18.75).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_agent_report_splits_handle_time_across_intervals() {
+        let queue_id = Uuid::new_v4();
+        let events = vec![
+            event(queue_id, "a", QueueEventType::Offered, at(10, 9, 50)).with_agent(7),
+            event(queue_id, "a", QueueEventType::Answered, at(10, 10, 0)).with_agent(7),
+            // Talked 10:10-10:20, wrap-up until 10:21
+            event(queue_id, "a", QueueEventType::Completed, at(10, 20, 0))
+                .with_agent(7)
+                .with_talk(Duration::from_secs(600))
+                .with_wrap_up(Duration::from_secs(60)),
+            event(queue_id, "b", QueueEventType::Offered, at(10, 25, 0)).with_agent(7),
+            // Another agent's call
+            event(queue_id, "c", QueueEventType::Answered, at(10, 5, 0)).with_agent(8),
+        ];
+
+        let report = build_agent_report(
+            7,
+            &events,
+            at(10, 0, 0),
+            at(10, 30, 0),
+            ChronoDuration::minutes(15),
+        );
+
+        let first = &report.intervals[0];
+        assert_eq!(first.offered, 1);
+        assert_eq!(first.answered, 1);
+        assert_eq!(first.talk_secs, 300);
+        assert!((first.occupancy - 100.0 / 3.0).abs() < 1e-9);
+
+        let second = &report.intervals[1];
+        assert_eq!(second.offered, 1);
+        assert_eq!(second.missed, 1);
+        assert_eq!(second.talk_secs, 300);
+        assert_eq!(second.wrap_up_secs, 60);
+        assert!((second.occupancy - 40.0).abs() < 1e-9);
+
+        assert_eq!(report.totals.answered, 1);
+        assert_eq!(report.totals.missed, 1);
+        assert_eq!(report.totals.talk_secs, 600);
+    }
+}
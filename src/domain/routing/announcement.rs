@@ -0,0 +1,692 @@
+//! Announcement-only numbers ("info lines")
+//!
+//! A number that only plays information, e.g. opening hours. The call is
+//! answered, the message for the local time of the number's tenant is
+//! played (possibly a few times) and the call is then hung up, sent to
+//! voicemail or forwarded, e.g. to a mobile. Time-conditioned messages are
+//! checked in order; the first whose range contains the local time wins,
+//! else the default message is played. Messages are audio library files
+//! (announcements or prompts) of the tenant or global ones.
+
+use crate::domain::audio::{AudioCategory, AudioLibrary, AudioReference};
+use crate::domain::routing::lint::{internal_target, DialRule, EntityKind, Lint, RoutingInventory};
+use crate::domain::routing::simulation::{Explain, Explanation, RouteOutcome, RoutedCall};
+use crate::domain::call_forwarding::TimeRange;
+use crate::domain::shared::time_zone::in_zone;
+use crate::domain::shared::TimeZoneConfig;
+use crate::domain::tenant_branding::realm_of;
+use crate::infrastructure::clock::{system_clock, Clock};
+use async_trait::async_trait;
+use chrono::{DateTime, Datelike, NaiveTime, Utc, Weekday};
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+use tracing::{info, warn};
+use uuid::Uuid;
+
+/// Reference kind recorded on audio files played by an announcement route
+const ROUTE_REFERENCE: &str = "announcement_route";
+
+/// Most plays of the message per call
+pub const MAX_REPEAT: u32 = 10;
+
+/// Seconds a message is assumed to last when its length is unknown
+const DEFAULT_MESSAGE_SECS: f64 = 10.0;
+
+/// Message played while the local time is within `hours`
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TimedMessage {
+    pub hours: TimeRange,
+    /// Audio library id of the message
+    pub audio_file: String,
+}
+
+/// What happens to the call once the message was played
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "action", rename_all = "snake_case")]
+pub enum FollowUp {
+    #[default]
+    Hangup,
+    /// Send the caller to a voicemail box of the tenant
+    Voicemail { mailbox: String },
+    /// Forward the call, e.g. to a mobile (number or SIP URI)
+    Forward { target: String },
+}
+
+impl FollowUp {
+    /// Destination the call is transferred to, `None` to hang up
+    ///
+    /// `voicemail_uri` is the voicemail URI template (`{user}` and
+    /// `{domain}` are replaced); plain numbers are dialed in `domain`.
+    pub fn target_uri(&self, domain: &str, voicemail_uri: &str) -> Option<String> {
+        match self {
+            FollowUp::Hangup => None,
+            FollowUp::Voicemail { mailbox } => Some(
+                voicemail_uri
+                    .replace("{user}", mailbox)
+                    .replace("{domain}", domain),
+            ),
+            FollowUp::Forward { target }
+                if target.starts_with("sip:") || target.starts_with("sips:") =>
+            {
+                Some(target.clone())
+            }
+            FollowUp::Forward { target } => Some(format!("sip:{}@{}", target, domain)),
+        }
+    }
+}
+
+/// Message variant played to a call
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MessageVariant {
+    /// Time-conditioned message at this (0-based) position
+    Timed(usize),
+    Default,
+}
+
+impl std::fmt::Display for MessageVariant {
+    /// As recorded on the CDR: the 1-based position, or "default"
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            MessageVariant::Timed(index) => write!(f, "{}", index + 1),
+            MessageVariant::Default => write!(f, "default"),
+        }
+    }
+}
+
+/// Announcement-only number
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct AnnouncementRoute {
+    pub id: Uuid,
+    /// Tenant (SIP realm) the number belongs to
+    pub tenant: String,
+    /// Dialed number or extension
+    pub number: String,
+    /// Checked in order
+    pub messages: Vec<TimedMessage>,
+    /// Audio library id of the message played outside every range
+    pub default_audio_file: String,
+    /// Times the message is played
+    pub repeat: u32,
+    pub follow_up: FollowUp,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+}
+
+impl AnnouncementRoute {
+    pub fn new(tenant: String, number: String, default_audio_file: String) -> Self {
+        let now = Utc::now();
+        Self {
+            id: Uuid::new_v4(),
+            tenant,
+            number,
+            messages: Vec::new(),
+            default_audio_file,
+            repeat: 1,
+            follow_up: FollowUp::Hangup,
+            created_at: now,
+            updated_at: now,
+        }
+    }
+
+    pub fn with_message(mut self, hours: TimeRange, audio_file: &str) -> Self {
+        self.messages.push(TimedMessage {
+            hours,
+            audio_file: audio_file.to_string(),
+        });
+        self
+    }
+
+    pub fn with_repeat(mut self, repeat: u32) -> Self {
+        self.repeat = repeat;
+        self
+    }
+
+    pub fn with_follow_up(mut self, follow_up: FollowUp) -> Self {
+        self.follow_up = follow_up;
+        self
+    }
+
+    /// Check the fields that do not depend on stored audio
+    pub fn validate(&self) -> Result<(), String> {
+        if self.tenant.is_empty() {
+            return Err("Tenant is required".to_string());
+        }
+        if self.number.is_empty()
+            || !self
+                .number
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || "+*#-_.".contains(c))
+        {
+            return Err(format!("Invalid number: {}", self.number));
+        }
+        if !(1..=MAX_REPEAT).contains(&self.repeat) {
+            return Err(format!("Repeat must be between 1 and {}", MAX_REPEAT));
+        }
+        if self.audio_files().any(str::is_empty) {
+            return Err("Every message needs an audio file".to_string());
+        }
+        match &self.follow_up {
+            FollowUp::Voicemail { mailbox } if mailbox.is_empty() => {
+                Err("Voicemail follow-up needs a mailbox".to_string())
+            }
+            FollowUp::Forward { target } if target.is_empty() => {
+                Err("Forward follow-up needs a target".to_string())
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Audio files of every message, the default one last
+    pub fn audio_files(&self) -> impl Iterator<Item = &str> {
+        self.messages
+            .iter()
+            .map(|m| m.audio_file.as_str())
+            .chain(std::iter::once(self.default_audio_file.as_str()))
+    }
+
+    /// Message played at local `time` on `weekday`
+    pub fn select(&self, time: NaiveTime, weekday: Weekday) -> (MessageVariant, &str) {
+        self.messages
+            .iter()
+            .enumerate()
+            .find(|(_, message)| message.hours.contains(time, weekday))
+            .map(|(index, message)| (MessageVariant::Timed(index), message.audio_file.as_str()))
+            .unwrap_or((MessageVariant::Default, &self.default_audio_file))
+    }
+}
+
+/// Announcement route repository trait
+#[cfg_attr(test, mockall::automock)]
+#[async_trait::async_trait]
+pub trait AnnouncementRouteRepository: Send + Sync {
+    async fn create(&self, route: &AnnouncementRoute) -> Result<(), String>;
+
+    async fn get_by_id(&self, id: Uuid) -> Result<Option<AnnouncementRoute>, String>;
+
+    /// Route of a tenant's number
+    async fn find_by_number(
+        &self,
+        tenant: &str,
+        number: &str,
+    ) -> Result<Option<AnnouncementRoute>, String>;
+
+    async fn list(&self) -> Result<Vec<AnnouncementRoute>, String>;
+
+    async fn update(&self, route: &AnnouncementRoute) -> Result<(), String>;
+
+    async fn delete(&self, id: Uuid) -> Result<(), String>;
+}
+
+/// Announcement route errors
+#[derive(Debug, Clone, PartialEq)]
+pub enum AnnouncementError {
+    Invalid(String),
+    NotFound(Uuid),
+    /// The tenant already has a route for the number
+    Duplicate(String),
+    Repository(String),
+}
+
+impl std::fmt::Display for AnnouncementError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            AnnouncementError::Invalid(e) => write!(f, "{}", e),
+            AnnouncementError::NotFound(id) => write!(f, "Announcement route not found: {}", id),
+            AnnouncementError::Duplicate(number) => {
+                write!(f, "Number {} already has an announcement route", number)
+            }
+            AnnouncementError::Repository(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl std::error::Error for AnnouncementError {}
+
+/// What to play to a call to an announcement-only number, and what follows
+#[derive(Debug, Clone, PartialEq)]
+pub struct PlannedAnnouncement {
+    pub route_id: Uuid,
+    pub variant: MessageVariant,
+    pub audio_file: String,
+    pub repeat: u32,
+    /// Seconds until the follow-up: every play of the message
+    pub play_secs: u64,
+    /// Transfer destination after the message, `None` to hang up
+    pub follow_up_uri: Option<String>,
+}
+
+/// Manages announcement routes and picks the message for a call
+pub struct AnnouncementService {
+    repository: Arc<dyn AnnouncementRouteRepository>,
+    /// Checks that messages exist and knows their length
+    library: Option<Arc<AudioLibrary>>,
+    /// Local time of the tenants
+    time_zones: TimeZoneConfig,
+    /// Voicemail URI template of voicemail follow-ups
+    voicemail_uri: String,
+    clock: Arc<dyn Clock>,
+}
+
+impl AnnouncementService {
+    pub fn new(repository: Arc<dyn AnnouncementRouteRepository>) -> Self {
+        Self {
+            repository,
+            library: None,
+            time_zones: TimeZoneConfig::default(),
+            voicemail_uri: "sip:vm-{user}@{domain}".to_string(),
+            clock: system_clock(),
+        }
+    }
+
+    /// Look messages up in the audio library
+    pub fn with_audio_library(mut self, library: Arc<AudioLibrary>) -> Self {
+        self.library = Some(library);
+        self
+    }
+
+    pub fn with_time_zones(mut self, time_zones: TimeZoneConfig) -> Self {
+        self.time_zones = time_zones;
+        self
+    }
+
+    pub fn with_voicemail_uri(mut self, voicemail_uri: String) -> Self {
+        self.voicemail_uri = voicemail_uri;
+        self
+    }
+
+    /// Time source of the message selection (the system clock by default)
+    pub fn with_clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    pub async fn list(&self) -> Result<Vec<AnnouncementRoute>, AnnouncementError> {
+        self.repository
+            .list()
+            .await
+            .map_err(AnnouncementError::Repository)
+    }
+
+    pub async fn get(&self, id: Uuid) -> Result<AnnouncementRoute, AnnouncementError> {
+        self.repository
+            .get_by_id(id)
+            .await
+            .map_err(AnnouncementError::Repository)?
+            .ok_or(AnnouncementError::NotFound(id))
+    }
+
+    /// Store a new route; its audio files are marked as referenced by it
+    pub async fn create(
+        &self,
+        route: AnnouncementRoute,
+    ) -> Result<AnnouncementRoute, AnnouncementError> {
+        self.check(&route).await?;
+        self.repository
+            .create(&route)
+            .await
+            .map_err(AnnouncementError::Repository)?;
+        self.reference(&route, true);
+        info!("Announcement route {} created for {}@{}", route.id, route.number, route.tenant);
+        Ok(route)
+    }
+
+    /// Replace a route's settings, keeping its id and creation time
+    pub async fn update(
+        &self,
+        id: Uuid,
+        route: AnnouncementRoute,
+    ) -> Result<AnnouncementRoute, AnnouncementError> {
+        let current = self.get(id).await?;
+        let route = AnnouncementRoute {
+            id,
+            created_at: current.created_at,
+            updated_at: Utc::now(),
+            ..route
+        };
+        self.check(&route).await?;
+        self.repository
+            .update(&route)
+            .await
+            .map_err(AnnouncementError::Repository)?;
+        self.reference(&current, false);
+        self.reference(&route, true);
+        info!("Announcement route {} updated", id);
+        Ok(route)
+    }
+
+    pub async fn delete(&self, id: Uuid) -> Result<(), AnnouncementError> {
+        let current = self.get(id).await?;
+        self.repository
+            .delete(id)
+            .await
+            .map_err(AnnouncementError::Repository)?;
+        self.reference(&current, false);
+        info!("Announcement route {} deleted", id);
+        Ok(())
+    }
+
+    /// Announcement for a call to `to_uri`, if it is an announcement-only
+    /// number
+    pub async fn resolve(&self, to_uri: &str) -> Option<PlannedAnnouncement> {
+        self.resolve_at(to_uri, self.clock.now()).await
+    }
+
+    /// Announcement for a call to `to_uri` placed at `at`
+    pub async fn resolve_at(&self, to_uri: &str, at: DateTime<Utc>) -> Option<PlannedAnnouncement> {
+        let tenant = realm_of(to_uri)?;
+        let number = to_uri
+            .trim_start_matches("sip:")
+            .trim_start_matches("sips:")
+            .split('@')
+            .next()?;
+        let route = match self.repository.find_by_number(tenant, number).await {
+            Ok(route) => route?,
+            Err(e) => {
+                warn!("Failed to look up announcement route for {}: {}", to_uri, e);
+                return None;
+            }
+        };
+
+        let local = in_zone(at, self.time_zones.zone_for(Some(tenant), None));
+        let (variant, audio_file) = route.select(local.time(), local.weekday());
+        let message_secs = self
+            .audio_scope(&route.tenant, audio_file)
+            .and_then(|owner| self.library.as_ref()?.get(owner.as_deref(), audio_file))
+            .map(|file| file.duration)
+            .unwrap_or(DEFAULT_MESSAGE_SECS);
+        Some(PlannedAnnouncement {
+            route_id: route.id,
+            variant,
+            audio_file: audio_file.to_string(),
+            repeat: route.repeat,
+            play_secs: (message_secs * route.repeat as f64).ceil() as u64,
+            follow_up_uri: route.follow_up.target_uri(tenant, &self.voicemail_uri),
+        })
+    }
+
+    /// Validate a route and check that its number is free and its audio
+    /// files exist
+    async fn check(&self, route: &AnnouncementRoute) -> Result<(), AnnouncementError> {
+        route.validate().map_err(AnnouncementError::Invalid)?;
+        let existing = self
+            .repository
+            .find_by_number(&route.tenant, &route.number)
+            .await
+            .map_err(AnnouncementError::Repository)?;
+        if existing.is_some_and(|existing| existing.id != route.id) {
+            return Err(AnnouncementError::Duplicate(route.number.clone()));
+        }
+        if self.library.is_none() {
+            return Err(AnnouncementError::Invalid(
+                "Audio library not available".to_string(),
+            ));
+        }
+        match route
+            .audio_files()
+            .find(|id| self.audio_scope(&route.tenant, id).is_none())
+        {
+            Some(id) => Err(AnnouncementError::Invalid(format!("Audio file {} not found", id))),
+            None => Ok(()),
+        }
+    }
+
+    /// Scope (tenant or global) holding announcement or prompt `id` as
+    /// seen by `tenant`
+    fn audio_scope(&self, tenant: &str, id: &str) -> Option<Option<String>> {
+        let library = self.library.as_ref()?;
+        [Some(tenant), None].into_iter().find_map(|owner| {
+            library
+                .get(owner, id)
+                .filter(|file| {
+                    matches!(file.category, AudioCategory::Announcement | AudioCategory::Prompt)
+                })
+                .map(|_| owner.map(str::to_string))
+        })
+    }
+
+    /// Mark (or unmark) the route's audio files as used by it
+    fn reference(&self, route: &AnnouncementRoute, add: bool) {
+        let Some(library) = &self.library else {
+            return;
+        };
+        let reference = AudioReference::new(ROUTE_REFERENCE, &route.id.to_string());
+        for id in route.audio_files() {
+            let Some(owner) = self.audio_scope(&route.tenant, id) else {
+                continue;
+            };
+            if add {
+                library.add_reference(owner.as_deref(), id, reference.clone());
+            } else {
+                library.remove_reference(owner.as_deref(), id, &reference);
+            }
+        }
+    }
+}
+
+#[async_trait]
+impl Explain for AnnouncementService {
+    fn step(&self) -> &'static str {
+        "announcement"
+    }
+
+    async fn explain(&self, call: &RoutedCall) -> Option<Explanation> {
+        let planned = self.resolve_at(&call.target_uri, call.at).await?;
+        Some(
+            Explanation::matched(format!(
+                "announcement route {} plays {} ({} message)",
+                planned.route_id, planned.audio_file, planned.variant
+            ))
+            .ending(RouteOutcome::Answered {
+                by: "announcement".to_string(),
+            }),
+        )
+    }
+}
+
+#[async_trait]
+impl Lint for AnnouncementService {
+    fn source(&self) -> &'static str {
+        "announcement"
+    }
+
+    /// Route numbers, their messages and where calls go afterwards; a
+    /// forward to an extension is always made
+    async fn inventory(&self, inventory: &mut RoutingInventory) {
+        let routes = match self.list().await {
+            Ok(routes) => routes,
+            Err(e) => {
+                warn!("Routing lint: failed to load announcement routes: {}", e);
+                return;
+            }
+        };
+        for route in routes {
+            let id = format!("announcement:{}", route.id);
+            let tenant = route.tenant.as_str();
+            inventory.rule(DialRule::exact(id.clone(), &route.number).with_tenant(tenant));
+            for audio_file in route.audio_files() {
+                inventory.reference(&id, EntityKind::AudioFile, Some(tenant), audio_file);
+            }
+            match &route.follow_up {
+                FollowUp::Hangup => {}
+                FollowUp::Voicemail { mailbox } => {
+                    inventory.reference(&id, EntityKind::Extension, Some(tenant), mailbox);
+                }
+                FollowUp::Forward { target } => {
+                    if let Some((user, Some(domain))) = internal_target(target, Some(tenant)) {
+                        inventory.reference(&id, EntityKind::Extension, Some(&domain), &user);
+                        inventory.forward(
+                            &id,
+                            &format!("{}@{}", route.number, tenant),
+                            &format!("{}@{}", user, domain),
+                        );
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::domain::audio::{AudioLibraryConfig, WavFile, WavFormat};
+    use crate::infrastructure::persistence::MemoryAnnouncementRouteRepository;
+    use crate::test_support::ManualClock;
+    use chrono::TimeZone;
+    use std::collections::HashMap;
+
+    fn wav(seconds: f64) -> Vec<u8> {
+        let samples = (8000.0 * seconds) as usize;
+        let data: Vec<u8> = (0..samples)
+            .flat_map(|i| (((i % 40) as i16 - 20) * 400).to_le_bytes())
+            .collect();
+        WavFile {
+            format: WavFormat {
+                channels: 1,
+                sample_rate: 8000,
+                bits_per_sample: 16,
+                audio_format: 1,
+            },
+            data: Arc::new(data),
+        }
+        .to_wav_bytes()
+    }
+
+    fn library() -> Arc<AudioLibrary> {
+        let root = std::env::temp_dir().join(format!("yakyak-announcement-{}", Uuid::new_v4()));
+        Arc::new(AudioLibrary::new(AudioLibraryConfig {
+            root,
+            ..Default::default()
+        }))
+    }
+
+    fn hours(start: u32, end: u32, days: Vec<Weekday>) -> TimeRange {
+        TimeRange::new(
+            NaiveTime::from_hms_opt(start, 0, 0).unwrap(),
+            NaiveTime::from_hms_opt(end, 0, 0).unwrap(),
+        )
+        .with_days(days)
+    }
+
+    /// Service for acme.test (Berlin time) at `now`, with an opening hours
+    /// line on 8000
+    async fn opening_hours_line(
+        now: DateTime<Utc>,
+        follow_up: FollowUp,
+    ) -> (AnnouncementService, AnnouncementRoute) {
+        let library = library();
+        let upload = |name: &str, seconds: f64| {
+            library
+                .upload(Some("acme.test"), name, AudioCategory::Announcement, &wav(seconds))
+                .unwrap()
+                .id
+        };
+        let (open, saturday, closed) = (upload("open", 1.5), upload("saturday", 0.5), upload("closed", 2.0));
+        let time_zones = TimeZoneConfig {
+            tenants: HashMap::from([("acme.test".to_string(), "Europe/Berlin".to_string())]),
+            ..Default::default()
+        };
+        let service = AnnouncementService::new(Arc::new(MemoryAnnouncementRouteRepository::new()))
+            .with_audio_library(library)
+            .with_time_zones(time_zones)
+            .with_clock(Arc::new(ManualClock::starting_at(now)));
+        let route = AnnouncementRoute::new("acme.test".to_string(), "8000".to_string(), closed)
+            .with_message(TimeRange::business_hours(), &open)
+            .with_message(hours(9, 13, vec![Weekday::Sat]), &saturday)
+            .with_repeat(2)
+            .with_follow_up(follow_up);
+        let route = service.create(route).await.unwrap();
+        (service, route)
+    }
+
+    #[tokio::test]
+    async fn test_message_selected_by_local_time() {
+        // Wednesday 10:00 in Berlin: open
+        let morning = Utc.with_ymd_and_hms(2026, 3, 4, 9, 0, 0).unwrap();
+        let (service, route) = opening_hours_line(morning, FollowUp::Hangup).await;
+        let planned = service.resolve("sip:8000@acme.test").await.unwrap();
+        assert_eq!(planned.route_id, route.id);
+        assert_eq!(planned.variant, MessageVariant::Timed(0));
+        assert_eq!(planned.variant.to_string(), "1");
+        assert_eq!(planned.audio_file, route.messages[0].audio_file);
+        assert_eq!(planned.play_secs, 3);
+
+        // 20:30 in Berlin the same day: closed
+        let evening = Utc.with_ymd_and_hms(2026, 3, 4, 19, 30, 0).unwrap();
+        let (service, route) = opening_hours_line(evening, FollowUp::Hangup).await;
+        let planned = service.resolve("sip:8000@acme.test").await.unwrap();
+        assert_eq!(planned.variant, MessageVariant::Default);
+        assert_eq!(planned.variant.to_string(), "default");
+        assert_eq!(planned.audio_file, route.default_audio_file);
+        assert_eq!(planned.play_secs, 4);
+
+        // Other numbers and tenants are routed as usual
+        assert_eq!(service.resolve("sip:8001@acme.test").await, None);
+        assert_eq!(service.resolve("sip:8000@globex.test").await, None);
+    }
+
+    #[tokio::test]
+    async fn test_follow_up_after_message() {
+        let now = Utc.with_ymd_and_hms(2026, 3, 4, 9, 0, 0).unwrap();
+        let (service, _) = opening_hours_line(now, FollowUp::Hangup).await;
+        let planned = service.resolve("sip:8000@acme.test").await.unwrap();
+        assert_eq!(planned.follow_up_uri, None);
+
+        let mobile = FollowUp::Forward {
+            target: "+4915112345678".to_string(),
+        };
+        let (service, _) = opening_hours_line(now, mobile).await;
+        let planned = service.resolve("sip:8000@acme.test").await.unwrap();
+        assert_eq!(
+            planned.follow_up_uri.as_deref(),
+            Some("sip:+4915112345678@acme.test")
+        );
+
+        let voicemail = FollowUp::Voicemail {
+            mailbox: "sales".to_string(),
+        };
+        assert_eq!(
+            voicemail.target_uri("acme.test", "sip:vm-{user}@{domain}").as_deref(),
+            Some("sip:vm-sales@acme.test")
+        );
+    }
+
+    #[tokio::test]
+    async fn test_routes_need_existing_audio_and_free_number() {
+        let now = Utc::now();
+        let (service, route) = opening_hours_line(now, FollowUp::Hangup).await;
+
+        let missing = AnnouncementRoute::new(
+            "acme.test".to_string(),
+            "8100".to_string(),
+            "missing".to_string(),
+        );
+        assert_eq!(
+            service.create(missing).await,
+            Err(AnnouncementError::Invalid("Audio file missing not found".to_string()))
+        );
+        let taken = AnnouncementRoute::new(
+            "acme.test".to_string(),
+            "8000".to_string(),
+            route.default_audio_file.clone(),
+        );
+        assert_eq!(
+            service.create(taken).await,
+            Err(AnnouncementError::Duplicate("8000".to_string()))
+        );
+        let no_mailbox = route.clone().with_follow_up(FollowUp::Voicemail {
+            mailbox: String::new(),
+        });
+        assert!(matches!(
+            service.update(route.id, no_mailbox).await,
+            Err(AnnouncementError::Invalid(_))
+        ));
+
+        service.delete(route.id).await.unwrap();
+        assert_eq!(service.resolve("sip:8000@acme.test").await, None);
+        assert_eq!(
+            service.get(route.id).await,
+            Err(AnnouncementError::NotFound(route.id))
+        );
+    }
+}
@@ -0,0 +1,759 @@
+//! Auto-attendants
+//!
+//! Reception menus such as "press 1 for sales, press 2 for support, or
+//! dial your party's extension; press 8 for dial-by-name". An attendant
+//! answers the numbers (DIDs) assigned to it, either always or only outside
+//! its business hours, and greets the caller with its day or night greeting
+//! depending on the tenant's local time. The menu runs on the IVR flow
+//! engine: the main menu collects extensions, and the dial-by-name menu
+//! searches the user directory by surname.
+
+use crate::domain::call_forwarding::TimeRange;
+use crate::domain::routing::announcement::FollowUp;
+use crate::domain::routing::lint::{internal_target, DialRule, EntityKind, Lint, RoutingInventory};
+use crate::domain::routing::simulation::{Explain, Explanation, RouteOutcome, RoutedCall};
+use crate::domain::shared::time_zone::in_zone;
+use crate::domain::shared::TimeZoneConfig;
+use crate::domain::tenant_branding::realm_of;
+use crate::infrastructure::clock::{system_clock, Clock};
+use crate::infrastructure::ivr::{
+    DialByName, ExtensionCollection, IvrFlow, IvrMenuBuilder, IvrMenuSystem, MenuAction,
+    MenuNode,
+};
+use async_trait::async_trait;
+use chrono::{DateTime, Datelike, NaiveTime, Utc, Weekday};
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+use tracing::{info, warn};
+use uuid::Uuid;
+
+/// Id of the attendant's main menu in its IVR flow
+pub const MAIN_MENU: &str = "main";
+
+/// Id of the dial-by-name menu in its IVR flow
+pub const DIAL_BY_NAME_MENU: &str = "dial_by_name";
+
+/// Greeting of the dial-by-name menu
+pub const DIAL_BY_NAME_GREETING: &str = "ivr/dbn_enter_name.wav";
+
+/// Number answered by an attendant
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AttendantNumber {
+    /// Dialed number (DID) or extension
+    pub number: String,
+    /// Answer only outside business hours; during them the number is
+    /// routed as usual
+    #[serde(default)]
+    pub after_hours: bool,
+}
+
+/// What a menu key does
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "action", rename_all = "snake_case")]
+pub enum AttendantAction {
+    /// Transfer to a number, extension or SIP URI
+    Forward { target: String },
+    /// Send the caller to a voicemail box of the tenant
+    Voicemail { mailbox: String },
+    /// Search the directory by surname
+    DialByName,
+    /// Play the greeting again
+    Repeat,
+    Hangup,
+}
+
+/// Key of the attendant's menu
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AttendantOption {
+    pub digit: char,
+    /// E.g. "Sales"
+    pub label: String,
+    #[serde(flatten)]
+    pub action: AttendantAction,
+}
+
+/// Auto-attendant of a tenant
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct AutoAttendant {
+    pub id: Uuid,
+    /// Tenant (SIP realm) the attendant belongs to
+    pub tenant: String,
+    pub name: String,
+    pub numbers: Vec<AttendantNumber>,
+    /// Prompt played within business hours
+    pub day_greeting: String,
+    /// Prompt played outside business hours (the day greeting if unset)
+    pub night_greeting: Option<String>,
+    /// Business hours; empty means always
+    pub business_hours: Vec<TimeRange>,
+    pub options: Vec<AttendantOption>,
+    /// Let callers dial an extension from the main menu
+    pub extension_dialing: Option<ExtensionCollection>,
+    /// Settings of the dial-by-name menu
+    pub dial_by_name: DialByName,
+    /// Wrong or missing inputs before the caller is hung up on
+    pub max_retries: u32,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+}
+
+impl AutoAttendant {
+    pub fn new(tenant: String, name: String, day_greeting: String) -> Self {
+        let now = Utc::now();
+        Self {
+            id: Uuid::new_v4(),
+            tenant,
+            name,
+            numbers: Vec::new(),
+            day_greeting,
+            night_greeting: None,
+            business_hours: Vec::new(),
+            options: Vec::new(),
+            extension_dialing: Some(ExtensionCollection::default()),
+            dial_by_name: DialByName::default(),
+            max_retries: 3,
+            created_at: now,
+            updated_at: now,
+        }
+    }
+
+    pub fn with_number(mut self, number: &str, after_hours: bool) -> Self {
+        self.numbers.push(AttendantNumber {
+            number: number.to_string(),
+            after_hours,
+        });
+        self
+    }
+
+    pub fn with_night_greeting(mut self, greeting: &str) -> Self {
+        self.night_greeting = Some(greeting.to_string());
+        self
+    }
+
+    pub fn with_business_hours(mut self, hours: TimeRange) -> Self {
+        self.business_hours.push(hours);
+        self
+    }
+
+    pub fn with_option(mut self, digit: char, label: &str, action: AttendantAction) -> Self {
+        self.options.push(AttendantOption {
+            digit,
+            label: label.to_string(),
+            action,
+        });
+        self
+    }
+
+    pub fn validate(&self) -> Result<(), String> {
+        if self.tenant.is_empty() {
+            return Err("Tenant is required".to_string());
+        }
+        if self.name.trim().is_empty() {
+            return Err("Name is required".to_string());
+        }
+        if self.day_greeting.is_empty() || self.night_greeting.as_ref().is_some_and(String::is_empty) {
+            return Err("Greetings must not be empty".to_string());
+        }
+        for (i, number) in self.numbers.iter().enumerate() {
+            if number.number.is_empty()
+                || !number
+                    .number
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || "+*#-_.".contains(c))
+            {
+                return Err(format!("Invalid number: {}", number.number));
+            }
+            if self.numbers[..i].iter().any(|n| n.number == number.number) {
+                return Err(format!("Number {} is listed twice", number.number));
+            }
+        }
+        for (i, option) in self.options.iter().enumerate() {
+            if !option.digit.is_ascii_digit() && option.digit != '*' && option.digit != '#' {
+                return Err(format!("Invalid key: {}", option.digit));
+            }
+            if self.options[..i].iter().any(|o| o.digit == option.digit) {
+                return Err(format!("Key {} is used twice", option.digit));
+            }
+            if option.digit == '#' && self.extension_dialing.is_some() {
+                return Err("Key # ends extensions and cannot be an option".to_string());
+            }
+            match &option.action {
+                AttendantAction::Forward { target } if target.is_empty() => {
+                    return Err(format!("Key {} needs a target", option.digit));
+                }
+                AttendantAction::Voicemail { mailbox } if mailbox.is_empty() => {
+                    return Err(format!("Key {} needs a mailbox", option.digit));
+                }
+                _ => {}
+            }
+        }
+        if let Some(collection) = &self.extension_dialing {
+            if !(1..=16).contains(&collection.max_digits) {
+                return Err("Extensions must have between 1 and 16 digits".to_string());
+            }
+            if !(1..=30).contains(&collection.inter_digit_timeout_secs) {
+                return Err("Inter-digit timeout must be between 1 and 30 seconds".to_string());
+            }
+        }
+        if !(1..=9).contains(&self.dial_by_name.max_choices) {
+            return Err("Dial-by-name reads back between 1 and 9 matches".to_string());
+        }
+        if self.dial_by_name.min_letters == 0 || self.dial_by_name.inter_digit_timeout_secs == 0 {
+            return Err("Dial-by-name needs at least one letter and a timeout".to_string());
+        }
+        if self.max_retries == 0 {
+            return Err("Max retries must be at least 1".to_string());
+        }
+        Ok(())
+    }
+
+    /// Whether local `time` on `weekday` is within business hours
+    pub fn is_open(&self, time: NaiveTime, weekday: Weekday) -> bool {
+        self.business_hours.is_empty()
+            || self.business_hours.iter().any(|hours| hours.contains(time, weekday))
+    }
+
+    /// Greeting played at local `time` on `weekday`
+    pub fn greeting(&self, time: NaiveTime, weekday: Weekday) -> &str {
+        if self.is_open(time, weekday) {
+            &self.day_greeting
+        } else {
+            self.night_greeting.as_deref().unwrap_or(&self.day_greeting)
+        }
+    }
+
+    /// Whether the attendant answers `number` at local `time` on `weekday`
+    pub fn answers(&self, number: &str, time: NaiveTime, weekday: Weekday) -> bool {
+        self.numbers
+            .iter()
+            .any(|n| n.number == number && (!n.after_hours || !self.is_open(time, weekday)))
+    }
+
+    /// IVR flow of the attendant, greeting with `greeting`
+    ///
+    /// `voicemail_uri` is the voicemail URI template (`{user}` and
+    /// `{domain}` are replaced); plain numbers are dialed in the tenant.
+    pub fn to_flow(&self, greeting: &str, voicemail_uri: &str) -> IvrFlow {
+        let main = self.options.iter().fold(
+            IvrMenuBuilder::new(MAIN_MENU.to_string(), self.name.clone(), greeting.to_string())
+                .max_retries(self.max_retries),
+            |menu, option| {
+                let action = match &option.action {
+                    AttendantAction::Forward { target } => FollowUp::Forward {
+                        target: target.clone(),
+                    }
+                    .target_uri(&self.tenant, voicemail_uri)
+                    .map_or(MenuAction::Hangup, MenuAction::Transfer),
+                    AttendantAction::Voicemail { mailbox } => FollowUp::Voicemail {
+                        mailbox: mailbox.clone(),
+                    }
+                    .target_uri(&self.tenant, voicemail_uri)
+                    .map_or(MenuAction::Hangup, MenuAction::Transfer),
+                    AttendantAction::DialByName => MenuAction::GotoMenu(DIAL_BY_NAME_MENU.to_string()),
+                    AttendantAction::Repeat => MenuAction::Repeat,
+                    AttendantAction::Hangup => MenuAction::Hangup,
+                };
+                menu.add_item(option.digit, option.label.clone(), action)
+            },
+        );
+        let main = match &self.extension_dialing {
+            Some(collection) => main.node(MenuNode::CollectExtension(collection.clone())),
+            None => main,
+        };
+
+        let mut menus = IvrMenuSystem::new();
+        menus.add_menu(main.build());
+        menus.add_menu(
+            IvrMenuBuilder::new(
+                DIAL_BY_NAME_MENU.to_string(),
+                "Dial by name".to_string(),
+                DIAL_BY_NAME_GREETING.to_string(),
+            )
+            .max_retries(self.max_retries)
+            .node(MenuNode::DialByName(self.dial_by_name.clone()))
+            .build(),
+        );
+        IvrFlow::new(
+            self.id.to_string(),
+            self.name.clone(),
+            MAIN_MENU.to_string(),
+            menus,
+        )
+        .with_realm(self.tenant.clone())
+    }
+}
+
+/// Auto-attendant repository trait
+#[cfg_attr(test, mockall::automock)]
+#[async_trait::async_trait]
+pub trait AutoAttendantRepository: Send + Sync {
+    async fn create(&self, attendant: &AutoAttendant) -> Result<(), String>;
+
+    async fn get_by_id(&self, id: Uuid) -> Result<Option<AutoAttendant>, String>;
+
+    /// Attendants of a tenant with `number` among their numbers
+    async fn find_by_number(&self, tenant: &str, number: &str) -> Result<Vec<AutoAttendant>, String>;
+
+    async fn list(&self) -> Result<Vec<AutoAttendant>, String>;
+
+    async fn update(&self, attendant: &AutoAttendant) -> Result<(), String>;
+
+    async fn delete(&self, id: Uuid) -> Result<(), String>;
+}
+
+/// Auto-attendant errors
+#[derive(Debug, Clone, PartialEq)]
+pub enum AutoAttendantError {
+    Invalid(String),
+    NotFound(Uuid),
+    /// Another attendant of the tenant answers the number
+    Duplicate(String),
+    Repository(String),
+}
+
+impl std::fmt::Display for AutoAttendantError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            AutoAttendantError::Invalid(e) => write!(f, "{}", e),
+            AutoAttendantError::NotFound(id) => write!(f, "Auto-attendant not found: {}", id),
+            AutoAttendantError::Duplicate(number) => {
+                write!(f, "Number {} already has an auto-attendant", number)
+            }
+            AutoAttendantError::Repository(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl std::error::Error for AutoAttendantError {}
+
+/// Attendant answering a call
+#[derive(Debug, Clone)]
+pub struct PlannedAttendant {
+    pub attendant_id: Uuid,
+    /// Day or night greeting
+    pub greeting: String,
+    pub flow: IvrFlow,
+}
+
+/// Manages auto-attendants and picks the one answering a call
+pub struct AutoAttendantService {
+    repository: Arc<dyn AutoAttendantRepository>,
+    /// Local time of the tenants
+    time_zones: TimeZoneConfig,
+    /// Voicemail URI template of voicemail keys
+    voicemail_uri: String,
+    clock: Arc<dyn Clock>,
+}
+
+impl AutoAttendantService {
+    pub fn new(repository: Arc<dyn AutoAttendantRepository>) -> Self {
+        Self {
+            repository,
+            time_zones: TimeZoneConfig::default(),
+            voicemail_uri: "sip:vm-{user}@{domain}".to_string(),
+            clock: system_clock(),
+        }
+    }
+
+    pub fn with_time_zones(mut self, time_zones: TimeZoneConfig) -> Self {
+        self.time_zones = time_zones;
+        self
+    }
+
+    pub fn with_voicemail_uri(mut self, voicemail_uri: String) -> Self {
+        self.voicemail_uri = voicemail_uri;
+        self
+    }
+
+    /// Time source of the greeting selection (the system clock by default)
+    pub fn with_clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    pub async fn list(&self) -> Result<Vec<AutoAttendant>, AutoAttendantError> {
+        self.repository
+            .list()
+            .await
+            .map_err(AutoAttendantError::Repository)
+    }
+
+    pub async fn get(&self, id: Uuid) -> Result<AutoAttendant, AutoAttendantError> {
+        self.repository
+            .get_by_id(id)
+            .await
+            .map_err(AutoAttendantError::Repository)?
+            .ok_or(AutoAttendantError::NotFound(id))
+    }
+
+    pub async fn create(&self, attendant: AutoAttendant) -> Result<AutoAttendant, AutoAttendantError> {
+        self.check(&attendant).await?;
+        self.repository
+            .create(&attendant)
+            .await
+            .map_err(AutoAttendantError::Repository)?;
+        info!(
+            "Auto-attendant {} ({}) created for {}",
+            attendant.id, attendant.name, attendant.tenant
+        );
+        Ok(attendant)
+    }
+
+    /// Replace an attendant's settings, keeping its id and creation time
+    pub async fn update(
+        &self,
+        id: Uuid,
+        attendant: AutoAttendant,
+    ) -> Result<AutoAttendant, AutoAttendantError> {
+        let current = self.get(id).await?;
+        let attendant = AutoAttendant {
+            id,
+            created_at: current.created_at,
+            updated_at: Utc::now(),
+            ..attendant
+        };
+        self.check(&attendant).await?;
+        self.repository
+            .update(&attendant)
+            .await
+            .map_err(AutoAttendantError::Repository)?;
+        info!("Auto-attendant {} updated", id);
+        Ok(attendant)
+    }
+
+    pub async fn delete(&self, id: Uuid) -> Result<(), AutoAttendantError> {
+        self.get(id).await?;
+        self.repository
+            .delete(id)
+            .await
+            .map_err(AutoAttendantError::Repository)?;
+        info!("Auto-attendant {} deleted", id);
+        Ok(())
+    }
+
+    /// Attendant answering a call to `to_uri` now, if any
+    pub async fn resolve(&self, to_uri: &str) -> Option<PlannedAttendant> {
+        self.resolve_at(to_uri, self.clock.now()).await
+    }
+
+    /// Attendant answering a call to `to_uri` placed at `at`
+    pub async fn resolve_at(&self, to_uri: &str, at: DateTime<Utc>) -> Option<PlannedAttendant> {
+        let tenant = realm_of(to_uri)?;
+        let number = to_uri
+            .trim_start_matches("sip:")
+            .trim_start_matches("sips:")
+            .split('@')
+            .next()?;
+        let attendants = match self.repository.find_by_number(tenant, number).await {
+            Ok(attendants) => attendants,
+            Err(e) => {
+                warn!("Failed to look up auto-attendant for {}: {}", to_uri, e);
+                return None;
+            }
+        };
+
+        let local = in_zone(at, self.time_zones.zone_for(Some(tenant), None));
+        let (time, weekday) = (local.time(), local.weekday());
+        let attendant = attendants
+            .into_iter()
+            .find(|attendant| attendant.answers(number, time, weekday))?;
+        let greeting = attendant.greeting(time, weekday).to_string();
+        Some(PlannedAttendant {
+            attendant_id: attendant.id,
+            flow: attendant.to_flow(&greeting, &self.voicemail_uri),
+            greeting,
+        })
+    }
+
+    /// Validate an attendant and check that no other attendant of the
+    /// tenant answers its numbers
+    async fn check(&self, attendant: &AutoAttendant) -> Result<(), AutoAttendantError> {
+        attendant.validate().map_err(AutoAttendantError::Invalid)?;
+        for number in &attendant.numbers {
+            let existing = self
+                .repository
+                .find_by_number(&attendant.tenant, &number.number)
+                .await
+                .map_err(AutoAttendantError::Repository)?;
+            if existing.iter().any(|other| other.id != attendant.id) {
+                return Err(AutoAttendantError::Duplicate(number.number.clone()));
+            }
+        }
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl Explain for AutoAttendantService {
+    fn step(&self) -> &'static str {
+        "auto_attendant"
+    }
+
+    async fn explain(&self, call: &RoutedCall) -> Option<Explanation> {
+        let planned = self.resolve_at(&call.target_uri, call.at).await?;
+        Some(
+            Explanation::matched(format!(
+                "auto-attendant {} greets with {}",
+                planned.attendant_id, planned.greeting
+            ))
+            .ending(RouteOutcome::Answered {
+                by: "auto_attendant".to_string(),
+            }),
+        )
+    }
+}
+
+#[async_trait]
+impl Lint for AutoAttendantService {
+    fn source(&self) -> &'static str {
+        "auto_attendant"
+    }
+
+    /// Attendant numbers (after-hours ones pass calls on during business
+    /// hours) and the extensions and mailboxes of menu keys
+    async fn inventory(&self, inventory: &mut RoutingInventory) {
+        let attendants = match self.list().await {
+            Ok(attendants) => attendants,
+            Err(e) => {
+                warn!("Routing lint: failed to load auto-attendants: {}", e);
+                return;
+            }
+        };
+        for attendant in attendants {
+            let id = format!("auto_attendant:{}", attendant.id);
+            let tenant = attendant.tenant.as_str();
+            for number in &attendant.numbers {
+                let rule = DialRule::exact(id.clone(), &number.number).with_tenant(tenant);
+                inventory.rule(if number.after_hours { rule.passing_on() } else { rule });
+            }
+            for option in &attendant.options {
+                match &option.action {
+                    AttendantAction::Forward { target } => {
+                        if let Some((user, domain)) = internal_target(target, Some(tenant)) {
+                            inventory.reference(&id, EntityKind::Extension, domain.as_deref(), &user);
+                        }
+                    }
+                    AttendantAction::Voicemail { mailbox } => {
+                        inventory.reference(&id, EntityKind::Extension, Some(tenant), mailbox);
+                    }
+                    _ => {}
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::infrastructure::ivr::directory::{
+        DirectoryEntry, IvrDirectory, CONFIRM_PROMPT, PRESS_PROMPT,
+    };
+    use crate::infrastructure::ivr::{DtmfDigit, DtmfEvent, IvrFlowEngine, IvrOutcome};
+    use crate::infrastructure::persistence::MemoryAutoAttendantRepository;
+    use crate::test_support::ManualClock;
+    use async_trait::async_trait;
+    use chrono::TimeZone;
+    use std::collections::HashMap;
+    use std::time::Duration;
+
+    /// Directory of acme.test
+    struct Staff(Vec<DirectoryEntry>);
+
+    #[async_trait]
+    impl IvrDirectory for Staff {
+        async fn find_extension(&self, _realm: Option<&str>, extension: &str) -> Option<DirectoryEntry> {
+            self.0.iter().find(|e| e.extension == extension).cloned()
+        }
+
+        async fn find_by_name(&self, _realm: Option<&str>, digits: &str) -> Vec<DirectoryEntry> {
+            self.0.iter().filter(|e| e.matches_keys(digits)).cloned().collect()
+        }
+    }
+
+    fn staff() -> Arc<Staff> {
+        let entry = |extension: &str, name: &str, name_prompt: Option<&str>| DirectoryEntry {
+            extension: extension.to_string(),
+            name: name.to_string(),
+            uri: format!("sip:{}@acme.test", extension),
+            name_prompt: name_prompt.map(str::to_string),
+        };
+        Arc::new(Staff(vec![
+            entry("1001", "Alice Smith", Some("names/1001.wav")),
+            entry("1002", "Bob Smiley", None),
+            entry("1003", "Carol Jones", None),
+        ]))
+    }
+
+    fn reception() -> AutoAttendant {
+        AutoAttendant::new("acme.test".to_string(), "Reception".to_string(), "day.wav".to_string())
+            .with_night_greeting("night.wav")
+            .with_business_hours(TimeRange::business_hours())
+            .with_number("+4930123456", false)
+            .with_number("8000", true)
+            .with_option('1', "Sales", AttendantAction::Forward { target: "2000".to_string() })
+            .with_option('2', "Support", AttendantAction::Voicemail { mailbox: "support".to_string() })
+            .with_option('8', "Directory", AttendantAction::DialByName)
+    }
+
+    /// Service for acme.test (Berlin time) at `now`
+    async fn reception_service(now: DateTime<Utc>) -> AutoAttendantService {
+        let time_zones = TimeZoneConfig {
+            tenants: HashMap::from([("acme.test".to_string(), "Europe/Berlin".to_string())]),
+            ..Default::default()
+        };
+        let service = AutoAttendantService::new(Arc::new(MemoryAutoAttendantRepository::new()))
+            .with_time_zones(time_zones)
+            .with_clock(Arc::new(ManualClock::starting_at(now)));
+        service.create(reception()).await.unwrap();
+        service
+    }
+
+    fn key(digit: char) -> DtmfEvent {
+        DtmfEvent::new(DtmfDigit::from_char(digit).unwrap(), Duration::from_millis(100))
+    }
+
+    #[test]
+    fn test_validate() {
+        assert!(reception().validate().is_ok());
+        assert!(reception()
+            .with_option('1', "Again", AttendantAction::Repeat)
+            .validate()
+            .unwrap_err()
+            .contains("used twice"));
+        assert!(reception()
+            .with_option('#', "Operator", AttendantAction::Forward { target: "0".to_string() })
+            .validate()
+            .is_err());
+        assert!(reception().with_number("8000", false).validate().is_err());
+    }
+
+    #[tokio::test]
+    async fn test_greeting_and_after_hours_number() {
+        // Wednesday 10:00 in Berlin: day greeting, the after-hours number
+        // is routed as usual
+        let service = reception_service(Utc.with_ymd_and_hms(2026, 3, 4, 9, 0, 0).unwrap()).await;
+        let planned = service.resolve("sip:+4930123456@acme.test").await.unwrap();
+        assert_eq!(planned.greeting, "day.wav");
+        assert!(service.resolve("sip:8000@acme.test").await.is_none());
+
+        // 20:30 in Berlin: night greeting on both numbers
+        let service = reception_service(Utc.with_ymd_and_hms(2026, 3, 4, 19, 30, 0).unwrap()).await;
+        let planned = service.resolve("sip:8000@acme.test").await.unwrap();
+        assert_eq!(planned.greeting, "night.wav");
+        assert!(service.resolve("sip:8000@globex.test").await.is_none());
+
+        // The number cannot go to a second attendant of the tenant
+        let other = AutoAttendant::new("acme.test".to_string(), "Other".to_string(), "x.wav".to_string())
+            .with_number("8000", false);
+        assert_eq!(
+            service.create(other).await.unwrap_err(),
+            AutoAttendantError::Duplicate("8000".to_string())
+        );
+    }
+
+    #[tokio::test]
+    async fn test_options_and_extension_dialing() {
+        let service = reception_service(Utc.with_ymd_and_hms(2026, 3, 4, 9, 0, 0).unwrap()).await;
+        let flow = service.resolve("sip:+4930123456@acme.test").await.unwrap().flow;
+        let engine = IvrFlowEngine::new().with_directory(staff());
+
+        // '1' alone, ended by the inter-digit timeout, selects Sales
+        engine.start_session("a".to_string(), &flow).await.unwrap();
+        assert_eq!(engine.process_dtmf("a", key('1'), &flow).await.unwrap(), MenuAction::Collecting);
+        assert_eq!(engine.input_timeout("a", &flow).await, Duration::from_secs(3));
+        assert_eq!(
+            engine.handle_input_timeout("a", &flow).await.unwrap(),
+            MenuAction::Transfer("sip:2000@acme.test".to_string())
+        );
+
+        // 1003# is an extension
+        engine.start_session("b".to_string(), &flow).await.unwrap();
+        for digit in ['1', '0', '0'] {
+            engine.process_dtmf("b", key(digit), &flow).await.unwrap();
+        }
+        engine.process_dtmf("b", key('3'), &flow).await.unwrap();
+        assert_eq!(
+            engine.process_dtmf("b", key('#'), &flow).await.unwrap(),
+            MenuAction::Transfer("sip:1003@acme.test".to_string())
+        );
+
+        // 1009# is not in the dial plan
+        engine.start_session("c".to_string(), &flow).await.unwrap();
+        for digit in ['1', '0', '0', '9'] {
+            engine.process_dtmf("c", key(digit), &flow).await.unwrap();
+        }
+        assert_eq!(
+            engine.process_dtmf("c", key('#'), &flow).await.unwrap(),
+            MenuAction::PlayPrompts(vec![
+                "ivr/invalid_extension.wav".to_string(),
+                "day.wav".to_string()
+            ])
+        );
+    }
+
+    #[tokio::test]
+    async fn test_dial_by_name_disambiguation() {
+        let service = reception_service(Utc.with_ymd_and_hms(2026, 3, 4, 9, 0, 0).unwrap()).await;
+        let flow = service.resolve("sip:+4930123456@acme.test").await.unwrap().flow;
+        let engine = IvrFlowEngine::new().with_directory(staff());
+        engine.start_session("call".to_string(), &flow).await.unwrap();
+
+        // '8' and the timeout open the directory
+        engine.process_dtmf("call", key('8'), &flow).await.unwrap();
+        assert_eq!(
+            engine.handle_input_timeout("call", &flow).await.unwrap(),
+            MenuAction::GotoMenu(DIAL_BY_NAME_MENU.to_string())
+        );
+
+        // S-M-I is 7-6-4 for both Smith and Smiley
+        assert_eq!(engine.process_dtmf("call", key('7'), &flow).await.unwrap(), MenuAction::Collecting);
+        assert_eq!(engine.process_dtmf("call", key('6'), &flow).await.unwrap(), MenuAction::Collecting);
+        let prompt = engine.process_dtmf("call", key('4'), &flow).await.unwrap();
+        let mut expected = vec![
+            "names/1001.wav".to_string(),
+            PRESS_PROMPT.to_string(),
+            "ivr/digits/1.wav".to_string(),
+        ];
+        expected.extend("BobSmiley".chars().map(|c| format!("ivr/letters/{}.wav", c.to_ascii_lowercase())));
+        expected.extend([PRESS_PROMPT.to_string(), "ivr/digits/2.wav".to_string()]);
+        assert_eq!(prompt, MenuAction::PlayPrompts(expected));
+
+        // A key that selects no one replays the choices
+        assert!(matches!(
+            engine.process_dtmf("call", key('5'), &flow).await.unwrap(),
+            MenuAction::PlayPrompts(_)
+        ));
+
+        assert_eq!(
+            engine.process_dtmf("call", key('2'), &flow).await.unwrap(),
+            MenuAction::Transfer("sip:1002@acme.test".to_string())
+        );
+    }
+
+    #[tokio::test]
+    async fn test_dial_by_name_single_match_is_confirmed() {
+        let service = reception_service(Utc.with_ymd_and_hms(2026, 3, 4, 9, 0, 0).unwrap()).await;
+        let flow = service.resolve("sip:+4930123456@acme.test").await.unwrap().flow;
+        let engine = IvrFlowEngine::new().with_directory(staff());
+        let (sender, mut digits) = tokio::sync::broadcast::channel(16);
+        // 8 for the directory, then J-O-N, 1 to connect
+        for digit in ['8', '#', '5', '6', '6', '1'] {
+            sender.send(key(digit)).unwrap();
+        }
+
+        let played = std::sync::Mutex::new(Vec::new());
+        let outcome = engine
+            .run("call", &flow, &mut digits, |prompts| {
+                played.lock().unwrap().push(prompts.to_vec())
+            })
+            .await;
+        assert_eq!(outcome, IvrOutcome::Transfer("sip:1003@acme.test".to_string()));
+
+        let played = played.into_inner().unwrap();
+        assert_eq!(played[0], vec![DIAL_BY_NAME_GREETING.to_string()]);
+        assert_eq!(played[1].last().unwrap(), CONFIRM_PROMPT);
+        assert_eq!(played[1][0], "ivr/letters/c.wav");
+        assert_eq!(engine.count_sessions().await, 0);
+    }
+}
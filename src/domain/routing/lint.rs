@@ -0,0 +1,876 @@
+//! Routing lint - static checks of the dial plan and routing configuration
+//!
+//! Each routing component describes through [`Lint`] the numbers it claims,
+//! the entities its rules refer to and the forwards it always makes.
+//! [`RoutingLinter`] collects them into one [`RoutingInventory`], in the
+//! order of the live INVITE path, and cross-checks it for rules no call
+//! can reach, feature codes colliding with extensions or other rules,
+//! references to things that do not exist and forwarding loops.
+
+use crate::domain::call_queue::CallQueueRepository;
+use crate::domain::shared::SortOrder;
+use crate::domain::sip_trunk::SipTrunkRepository;
+use crate::domain::user::UserRepository;
+use async_trait::async_trait;
+use serde::Serialize;
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::sync::Arc;
+use tracing::warn;
+
+/// How bad a finding is
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum LintSeverity {
+    Info,
+    /// Configuration that does not do what it says
+    Warning,
+    /// Calls fail or never end
+    Error,
+}
+
+impl fmt::Display for LintSeverity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LintSeverity::Info => write!(f, "info"),
+            LintSeverity::Warning => write!(f, "warning"),
+            LintSeverity::Error => write!(f, "error"),
+        }
+    }
+}
+
+/// Class of a finding
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum LintProblem {
+    /// An earlier rule matches every number of a later one
+    Shadowed,
+    /// A feature code could be meant for an extension or another rule
+    Overlap,
+    /// A rule refers to something that does not exist
+    Dangling,
+    /// Static forwards lead back to where they started
+    Loop,
+}
+
+impl LintProblem {
+    fn severity(&self) -> LintSeverity {
+        match self {
+            LintProblem::Shadowed => LintSeverity::Warning,
+            LintProblem::Overlap | LintProblem::Dangling | LintProblem::Loop => LintSeverity::Error,
+        }
+    }
+}
+
+/// One problem of the routing configuration
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct LintFinding {
+    pub severity: LintSeverity,
+    pub problem: LintProblem,
+    /// Ids of the rules involved
+    pub rules: Vec<String>,
+    pub message: String,
+}
+
+impl LintFinding {
+    fn new(problem: LintProblem, rules: Vec<String>, message: String) -> Self {
+        Self {
+            severity: problem.severity(),
+            problem,
+            rules,
+            message,
+        }
+    }
+}
+
+impl fmt::Display for LintFinding {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {} [{}]", self.severity, self.message, self.rules.join(", "))
+    }
+}
+
+/// Findings of a lint run
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct LintReport {
+    /// Components that were checked, in routing order
+    pub sources: Vec<String>,
+    pub rules: usize,
+    pub findings: Vec<LintFinding>,
+}
+
+impl LintReport {
+    pub fn has_errors(&self) -> bool {
+        self.count(LintSeverity::Error) > 0
+    }
+
+    pub fn count(&self, severity: LintSeverity) -> usize {
+        self.findings.iter().filter(|f| f.severity == severity).count()
+    }
+}
+
+impl fmt::Display for LintReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "Checked {} rules of {}",
+            self.rules,
+            self.sources.join(", ")
+        )?;
+        for finding in &self.findings {
+            writeln!(f, "  {}", finding)?;
+        }
+        write!(
+            f,
+            "{} errors, {} warnings",
+            self.count(LintSeverity::Error),
+            self.count(LintSeverity::Warning)
+        )
+    }
+}
+
+/// Numbers a rule matches
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DialPattern {
+    /// This number only
+    Exact(String),
+    /// This prefix followed by one or more characters
+    Prefix(String),
+}
+
+impl DialPattern {
+    /// Whether every number `other` matches is matched by this pattern
+    pub fn covers(&self, other: &DialPattern) -> bool {
+        match (self, other) {
+            (DialPattern::Exact(a), DialPattern::Exact(b)) => a == b,
+            (DialPattern::Exact(_), DialPattern::Prefix(_)) => false,
+            (DialPattern::Prefix(p), DialPattern::Exact(b)) => b.len() > p.len() && b.starts_with(p.as_str()),
+            (DialPattern::Prefix(p), DialPattern::Prefix(q)) => q.starts_with(p.as_str()),
+        }
+    }
+
+    /// Whether some number is matched by both patterns
+    pub fn intersects(&self, other: &DialPattern) -> bool {
+        match (self, other) {
+            (DialPattern::Prefix(p), DialPattern::Prefix(q)) => {
+                p.starts_with(q.as_str()) || q.starts_with(p.as_str())
+            }
+            _ => self.covers(other) || other.covers(self),
+        }
+    }
+}
+
+impl fmt::Display for DialPattern {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DialPattern::Exact(number) => write!(f, "{}", number),
+            DialPattern::Prefix(prefix) => write!(f, "{}…", prefix),
+        }
+    }
+}
+
+/// A rule claiming dialed numbers
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DialRule {
+    pub id: String,
+    /// Component the rule belongs to, set when it is added
+    pub step: &'static str,
+    /// Tenant whose callers the rule applies to; every tenant when unset
+    pub tenant: Option<String>,
+    pub pattern: DialPattern,
+    /// Whether calls go on to the next step after the rule matched
+    /// (rewrites, header rules) rather than ending routing there
+    pub passes_on: bool,
+}
+
+impl DialRule {
+    pub fn exact(id: impl Into<String>, number: &str) -> Self {
+        Self::new(id.into(), DialPattern::Exact(number.to_string()))
+    }
+
+    pub fn prefix(id: impl Into<String>, prefix: &str) -> Self {
+        Self::new(id.into(), DialPattern::Prefix(prefix.to_string()))
+    }
+
+    fn new(id: String, pattern: DialPattern) -> Self {
+        Self {
+            id,
+            step: "",
+            tenant: None,
+            pattern,
+            passes_on: false,
+        }
+    }
+
+    pub fn with_tenant(mut self, tenant: &str) -> Self {
+        self.tenant = Some(tenant.to_string());
+        self
+    }
+
+    pub fn passing_on(mut self) -> Self {
+        self.passes_on = true;
+        self
+    }
+}
+
+/// Things routing rules refer to
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum EntityKind {
+    Extension,
+    AudioFile,
+    Queue,
+    Trunk,
+}
+
+impl fmt::Display for EntityKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            EntityKind::Extension => write!(f, "extension"),
+            EntityKind::AudioFile => write!(f, "audio file"),
+            EntityKind::Queue => write!(f, "queue"),
+            EntityKind::Trunk => write!(f, "trunk"),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+struct Reference {
+    rule: String,
+    kind: EntityKind,
+    tenant: Option<String>,
+    name: String,
+}
+
+#[derive(Debug, Clone)]
+struct Forward {
+    rule: String,
+    from: String,
+    to: String,
+}
+
+/// What the routing components told the linter
+#[derive(Debug, Default)]
+pub struct RoutingInventory {
+    source: &'static str,
+    rules: Vec<DialRule>,
+    /// Tenant rules replacing the tenant-less rule of the same id
+    overridden: HashSet<(String, String)>,
+    /// Existing entities by tenant (`None` for global ones)
+    known: HashMap<EntityKind, HashSet<(Option<String>, String)>>,
+    references: Vec<Reference>,
+    forwards: Vec<Forward>,
+}
+
+impl RoutingInventory {
+    /// Add a rule of the current component; rules of a component are
+    /// matched in the order added
+    pub fn rule(&mut self, mut rule: DialRule) {
+        rule.step = self.source;
+        if let Some(tenant) = &rule.tenant {
+            self.overridden.insert((rule.id.clone(), tenant.clone()));
+        }
+        self.rules.push(rule);
+    }
+
+    /// Disable the tenant-less rule `id` for `tenant`
+    pub fn disable(&mut self, id: &str, tenant: &str) {
+        self.overridden.insert((id.to_string(), tenant.to_string()));
+    }
+
+    /// Declare that every existing entity of `kind` is reported, so
+    /// references to others are dangling
+    pub fn provide(&mut self, kind: EntityKind) {
+        self.known.entry(kind).or_default();
+    }
+
+    /// Record an existing entity
+    pub fn known(&mut self, kind: EntityKind, tenant: Option<&str>, name: &str) {
+        self.known
+            .entry(kind)
+            .or_default()
+            .insert((tenant.map(str::to_string), name.to_string()));
+    }
+
+    /// Record that rule `rule` refers to an entity
+    pub fn reference(&mut self, rule: &str, kind: EntityKind, tenant: Option<&str>, name: &str) {
+        self.references.push(Reference {
+            rule: rule.to_string(),
+            kind,
+            tenant: tenant.map(str::to_string),
+            name: name.to_string(),
+        });
+    }
+
+    /// Record that rule `rule` always sends calls for `from` on to `to`
+    /// (both `user@domain`)
+    pub fn forward(&mut self, rule: &str, from: &str, to: &str) {
+        self.forwards.push(Forward {
+            rule: rule.to_string(),
+            from: from.to_ascii_lowercase(),
+            to: to.to_ascii_lowercase(),
+        });
+    }
+
+    /// Whether rule `rule` applies to callers of `tenant`
+    fn applies(&self, rule: &DialRule, tenant: Option<&str>) -> bool {
+        match (&rule.tenant, tenant) {
+            (None, None) => true,
+            (None, Some(tenant)) => !self.overridden.contains(&(rule.id.clone(), tenant.to_string())),
+            (Some(own), Some(tenant)) => own == tenant,
+            (Some(_), None) => false,
+        }
+    }
+
+    /// Whether a referenced entity exists, `None` when it cannot be told
+    fn resolves(&self, reference: &Reference) -> Option<bool> {
+        let known = self.known.get(&reference.kind)?;
+        let tenant = match &reference.tenant {
+            Some(tenant) => tenant,
+            None => return Some(known.iter().any(|(_, name)| *name == reference.name)),
+        };
+        // Extensions of domains with no known user are on other systems
+        if reference.kind == EntityKind::Extension
+            && !known.iter().any(|(owner, _)| owner.as_ref() == Some(tenant))
+        {
+            return None;
+        }
+        Some(
+            known.contains(&(Some(tenant.clone()), reference.name.clone()))
+                || known.contains(&(None, reference.name.clone())),
+        )
+    }
+}
+
+/// A routing component describing its configuration
+#[async_trait]
+pub trait Lint: Send + Sync {
+    /// Name of the component in findings
+    fn source(&self) -> &'static str;
+
+    /// Add the component's rules, references and known entities
+    async fn inventory(&self, inventory: &mut RoutingInventory);
+}
+
+/// Cross-checks the configuration of the routing components
+#[derive(Default)]
+pub struct RoutingLinter {
+    sources: Vec<Arc<dyn Lint>>,
+}
+
+impl RoutingLinter {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Append a component; those claiming numbers are added in the order
+    /// calls go through them
+    pub fn with_source(mut self, source: Arc<dyn Lint>) -> Self {
+        self.sources.push(source);
+        self
+    }
+
+    pub async fn lint(&self) -> LintReport {
+        let mut inventory = RoutingInventory::default();
+        for source in &self.sources {
+            inventory.source = source.source();
+            source.inventory(&mut inventory).await;
+        }
+
+        let mut findings = shadowed(&inventory);
+        findings.extend(overlaps(&inventory));
+        findings.extend(dangling(&inventory));
+        findings.extend(loops(&inventory));
+        findings.sort_by_key(|f| std::cmp::Reverse(f.severity));
+        LintReport {
+            sources: self.sources.iter().map(|s| s.source().to_string()).collect(),
+            rules: inventory.rules.len(),
+            findings,
+        }
+    }
+}
+
+/// Rules an earlier rule always takes calls from: one of the same
+/// component (first match wins) or one of an earlier component ending
+/// routing
+fn shadowed(inventory: &RoutingInventory) -> Vec<LintFinding> {
+    let mut findings = Vec::new();
+    for (i, rule) in inventory.rules.iter().enumerate() {
+        let tenant = rule.tenant.as_deref();
+        let earlier = inventory.rules[..i].iter().find(|earlier| {
+            (earlier.step == rule.step || !earlier.passes_on)
+                && earlier.id != rule.id
+                && inventory.applies(earlier, tenant)
+                && earlier.pattern.covers(&rule.pattern)
+        });
+        if let Some(earlier) = earlier {
+            findings.push(LintFinding::new(
+                LintProblem::Shadowed,
+                vec![earlier.id.clone(), rule.id.clone()],
+                format!(
+                    "{} {} is never reached: {} {} matches every number it does",
+                    rule.step, rule.pattern, earlier.step, earlier.pattern
+                ),
+            ));
+        }
+    }
+    findings
+}
+
+/// Feature codes that could be meant for an extension or a rule of
+/// another component
+fn overlaps(inventory: &RoutingInventory) -> Vec<LintFinding> {
+    let extensions = inventory.known.get(&EntityKind::Extension);
+    let mut findings = Vec::new();
+    for (i, code) in inventory.rules.iter().enumerate() {
+        if code.step != FEATURE_CODE_STEP {
+            continue;
+        }
+        for extension in extensions.into_iter().flatten() {
+            let (tenant, name) = extension;
+            if (code.tenant.is_none() || code.tenant == *tenant)
+                && code.pattern.intersects(&DialPattern::Exact(name.clone()))
+            {
+                findings.push(LintFinding::new(
+                    LintProblem::Overlap,
+                    vec![code.id.clone()],
+                    format!(
+                        "feature code {} overlaps extension {}{}",
+                        code.pattern,
+                        name,
+                        tenant.as_ref().map(|t| format!("@{}", t)).unwrap_or_default()
+                    ),
+                ));
+            }
+        }
+        for (j, other) in inventory.rules.iter().enumerate() {
+            if other.step == FEATURE_CODE_STEP
+                || !code.pattern.intersects(&other.pattern)
+                || !inventory.applies(code, other.tenant.as_deref())
+            {
+                continue;
+            }
+            // Rules a feature code takes every call from are shadowed
+            if j > i && code.pattern.covers(&other.pattern) && !code.passes_on {
+                continue;
+            }
+            findings.push(LintFinding::new(
+                LintProblem::Overlap,
+                vec![code.id.clone(), other.id.clone()],
+                format!(
+                    "feature code {} overlaps {} {}",
+                    code.pattern, other.step, other.pattern
+                ),
+            ));
+        }
+    }
+    findings
+}
+
+/// References to entities that do not exist
+fn dangling(inventory: &RoutingInventory) -> Vec<LintFinding> {
+    inventory
+        .references
+        .iter()
+        .filter(|reference| inventory.resolves(reference) == Some(false))
+        .map(|reference| {
+            LintFinding::new(
+                LintProblem::Dangling,
+                vec![reference.rule.clone()],
+                format!(
+                    "{} {}{} does not exist",
+                    reference.kind,
+                    reference.name,
+                    reference
+                        .tenant
+                        .as_ref()
+                        .map(|t| format!(" in {}", t))
+                        .unwrap_or_default()
+                ),
+            )
+        })
+        .collect()
+}
+
+/// Cycles of static forwards, each reported once
+fn loops(inventory: &RoutingInventory) -> Vec<LintFinding> {
+    let mut edges: HashMap<&str, Vec<&Forward>> = HashMap::new();
+    for forward in &inventory.forwards {
+        edges.entry(forward.from.as_str()).or_default().push(forward);
+    }
+
+    let mut seen = HashSet::new();
+    let mut findings = Vec::new();
+    let mut starts: Vec<&str> = edges.keys().copied().collect();
+    starts.sort();
+    for start in starts {
+        let mut path: Vec<&Forward> = Vec::new();
+        find_cycles(start, &edges, &mut path, &mut seen, &mut findings);
+    }
+    findings
+}
+
+fn find_cycles<'a>(
+    at: &str,
+    edges: &HashMap<&str, Vec<&'a Forward>>,
+    path: &mut Vec<&'a Forward>,
+    seen: &mut HashSet<Vec<String>>,
+    findings: &mut Vec<LintFinding>,
+) {
+    for &forward in edges.get(at).into_iter().flatten() {
+        if let Some(start) = path.iter().position(|f| f.from == forward.to) {
+            let cycle: Vec<&Forward> = path[start..].iter().copied().chain([forward]).collect();
+            let mut key: Vec<String> = cycle.iter().map(|f| f.rule.clone()).collect();
+            key.sort();
+            if seen.insert(key) {
+                let mut hops: Vec<&str> = cycle.iter().map(|f| f.from.as_str()).collect();
+                hops.push(forward.to.as_str());
+                findings.push(LintFinding::new(
+                    LintProblem::Loop,
+                    cycle.iter().map(|f| f.rule.clone()).collect(),
+                    format!("calls are forwarded in a loop: {}", hops.join(" -> ")),
+                ));
+            }
+            continue;
+        }
+        if forward.from == forward.to {
+            if seen.insert(vec![forward.rule.clone()]) {
+                findings.push(LintFinding::new(
+                    LintProblem::Loop,
+                    vec![forward.rule.clone()],
+                    format!("calls to {} are forwarded to themselves", forward.from),
+                ));
+            }
+            continue;
+        }
+        path.push(forward);
+        find_cycles(&forward.to, edges, path, seen, findings);
+        path.pop();
+    }
+}
+
+/// Users, trunks and queues routing rules may refer to
+#[derive(Default)]
+pub struct RoutingDirectory {
+    users: Option<Arc<dyn UserRepository>>,
+    trunks: Option<Arc<dyn SipTrunkRepository>>,
+    queues: Option<Arc<dyn CallQueueRepository>>,
+}
+
+impl RoutingDirectory {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_users(mut self, users: Arc<dyn UserRepository>) -> Self {
+        self.users = Some(users);
+        self
+    }
+
+    pub fn with_trunks(mut self, trunks: Arc<dyn SipTrunkRepository>) -> Self {
+        self.trunks = Some(trunks);
+        self
+    }
+
+    pub fn with_queues(mut self, queues: Arc<dyn CallQueueRepository>) -> Self {
+        self.queues = Some(queues);
+        self
+    }
+}
+
+#[async_trait]
+impl Lint for RoutingDirectory {
+    fn source(&self) -> &'static str {
+        "directory"
+    }
+
+    /// Entities of a repository that cannot be read are not checked
+    async fn inventory(&self, inventory: &mut RoutingInventory) {
+        if let Some(users) = &self.users {
+            let sort = SortOrder::ascending("id");
+            let mut offset = 0;
+            loop {
+                let page = match users.list(&sort, USER_PAGE, offset).await {
+                    Ok(page) => page,
+                    Err(e) => {
+                        warn!("Routing lint: failed to list users: {}", e);
+                        break;
+                    }
+                };
+                inventory.provide(EntityKind::Extension);
+                for user in &page {
+                    inventory.known(EntityKind::Extension, Some(&user.realm), &user.username);
+                }
+                if (page.len() as i64) < USER_PAGE {
+                    break;
+                }
+                offset += USER_PAGE;
+            }
+        }
+        if let Some(trunks) = &self.trunks {
+            match trunks.list_trunks(false).await {
+                Ok(trunks) => {
+                    inventory.provide(EntityKind::Trunk);
+                    for trunk in trunks {
+                        inventory.known(EntityKind::Trunk, None, &trunk.name);
+                    }
+                }
+                Err(e) => warn!("Routing lint: failed to list trunks: {}", e),
+            }
+        }
+        if let Some(queues) = &self.queues {
+            match queues.list_queues().await {
+                Ok(queues) => {
+                    inventory.provide(EntityKind::Queue);
+                    for queue in queues {
+                        inventory.known(EntityKind::Queue, None, &queue.id.to_string());
+                        // Queues are dialed at their extension
+                        inventory.known(EntityKind::Extension, None, &queue.extension);
+                    }
+                }
+                Err(e) => warn!("Routing lint: failed to list queues: {}", e),
+            }
+        }
+    }
+}
+
+/// Users listed per repository call
+const USER_PAGE: i64 = 500;
+
+/// Step name of feature codes, whose overlaps are checked
+pub const FEATURE_CODE_STEP: &str = "feature_code";
+
+/// User and domain of a call target (the domain is `tenant` for targets
+/// without one); `None` for targets that are not internal extensions
+/// (external numbers, URIs of other schemes)
+pub fn internal_target(target: &str, tenant: Option<&str>) -> Option<(String, Option<String>)> {
+    let target = target.trim();
+    let rest = target
+        .strip_prefix("sips:")
+        .or_else(|| target.strip_prefix("sip:"))
+        .unwrap_or(target);
+    if rest.contains(':') && !rest.contains('@') {
+        return None;
+    }
+    let (user, domain) = match rest.split_once('@') {
+        Some((user, host)) => (user, host.split([';', ':', '>']).next()),
+        None => (rest, tenant),
+    };
+    let user = user.split(';').next().unwrap_or(user);
+    let extension = if user.chars().all(|c| c.is_ascii_digit()) {
+        !user.is_empty() && user.len() <= MAX_EXTENSION_DIGITS
+    } else {
+        !user.starts_with('+') && user.chars().any(|c| c.is_ascii_alphabetic())
+    };
+    extension.then(|| (user.to_string(), domain.map(str::to_ascii_lowercase)))
+}
+
+/// Longer numbers are dialed out
+const MAX_EXTENSION_DIGITS: usize = 6;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Rules and references given as is
+    struct Fixture {
+        source: &'static str,
+        fill: fn(&mut RoutingInventory),
+    }
+
+    #[async_trait]
+    impl Lint for Fixture {
+        fn source(&self) -> &'static str {
+            self.source
+        }
+
+        async fn inventory(&self, inventory: &mut RoutingInventory) {
+            (self.fill)(inventory)
+        }
+    }
+
+    fn fixture(source: &'static str, fill: fn(&mut RoutingInventory)) -> Arc<dyn Lint> {
+        Arc::new(Fixture { source, fill })
+    }
+
+    fn problems(report: &LintReport) -> Vec<(LintProblem, LintSeverity, Vec<String>)> {
+        report
+            .findings
+            .iter()
+            .map(|f| (f.problem, f.severity, f.rules.clone()))
+            .collect()
+    }
+
+    #[test]
+    fn test_patterns() {
+        let prefix = DialPattern::Prefix("00".to_string());
+        assert!(prefix.covers(&DialPattern::Exact("0049".to_string())));
+        assert!(!prefix.covers(&DialPattern::Exact("00".to_string())));
+        assert!(prefix.covers(&DialPattern::Prefix("0044".to_string())));
+        assert!(!DialPattern::Prefix("0044".to_string()).covers(&prefix));
+        assert!(DialPattern::Prefix("0044".to_string()).intersects(&prefix));
+        assert!(!DialPattern::Exact("100".to_string()).intersects(&DialPattern::Exact("101".to_string())));
+
+        assert_eq!(
+            internal_target("sip:2001@Acme.test", Some("other.test")),
+            Some(("2001".to_string(), Some("acme.test".to_string())))
+        );
+        assert_eq!(
+            internal_target("alice", Some("acme.test")),
+            Some(("alice".to_string(), Some("acme.test".to_string())))
+        );
+        assert_eq!(internal_target("1000", None), Some(("1000".to_string(), None)));
+        assert_eq!(internal_target("004930123456", Some("acme.test")), None);
+        assert_eq!(internal_target("+4930123456", Some("acme.test")), None);
+    }
+
+    #[tokio::test]
+    async fn test_one_finding_per_problem_class() {
+        let report = RoutingLinter::new()
+            .with_source(fixture("feature_code", |inv| {
+                inv.rule(DialRule::exact("feature_code:dnd.on", "*78"));
+                inv.rule(DialRule::prefix("feature_code:intercom", "*8"));
+            }))
+            .with_source(fixture("speed_dial", |inv| {
+                inv.rule(DialRule::exact("speed_dial:reception", "*80").passing_on());
+            }))
+            .with_source(fixture("announcement", |inv| {
+                inv.rule(DialRule::exact("announcement:hours", "500").with_tenant("acme.test"));
+                inv.reference("announcement:hours", EntityKind::AudioFile, Some("acme.test"), "closed");
+                inv.forward("announcement:hours", "500@acme.test", "600@acme.test");
+            }))
+            .with_source(fixture("auto_attendant", |inv| {
+                inv.rule(DialRule::exact("auto_attendant:main", "600").with_tenant("acme.test"));
+                inv.forward("auto_attendant:main", "600@acme.test", "500@acme.test");
+            }))
+            .with_source(fixture("header_rules", |inv| {
+                inv.rule(DialRule::prefix("header_route:international", "00").passing_on());
+                inv.rule(DialRule::prefix("header_route:germany", "0049").passing_on());
+                inv.reference("header_route:international", EntityKind::Trunk, None, "carrier");
+            }))
+            .with_source(fixture("directory", |inv| {
+                inv.known(EntityKind::Extension, Some("acme.test"), "2001");
+                inv.known(EntityKind::AudioFile, Some("acme.test"), "welcome");
+                inv.provide(EntityKind::Trunk);
+            }))
+            .lint()
+            .await;
+
+        assert_eq!(report.rules, 7);
+        let problems = problems(&report);
+        assert!(problems.contains(&(
+            LintProblem::Shadowed,
+            LintSeverity::Warning,
+            vec!["feature_code:intercom".to_string(), "speed_dial:reception".to_string()],
+        )));
+        assert!(problems.contains(&(
+            LintProblem::Shadowed,
+            LintSeverity::Warning,
+            vec!["header_route:international".to_string(), "header_route:germany".to_string()],
+        )));
+        assert!(problems.contains(&(
+            LintProblem::Dangling,
+            LintSeverity::Error,
+            vec!["announcement:hours".to_string()],
+        )));
+        assert!(problems.contains(&(
+            LintProblem::Dangling,
+            LintSeverity::Error,
+            vec!["header_route:international".to_string()],
+        )));
+        assert!(problems.contains(&(
+            LintProblem::Loop,
+            LintSeverity::Error,
+            vec!["announcement:hours".to_string(), "auto_attendant:main".to_string()],
+        )));
+        assert_eq!(report.findings.len(), 5);
+        assert!(report.has_errors());
+        assert_eq!(report.findings[0].severity, LintSeverity::Error);
+    }
+
+    #[tokio::test]
+    async fn test_feature_code_overlaps() {
+        let report = RoutingLinter::new()
+            .with_source(fixture("feature_code", |inv| {
+                inv.rule(DialRule::exact("feature_code:dnd.on", "*78"));
+                inv.rule(DialRule::prefix("feature_code:pickup", "*9"));
+                inv.rule(DialRule::prefix("feature_code:pickup", "*5").with_tenant("acme.test"));
+            }))
+            .with_source(fixture("announcement", |inv| {
+                inv.rule(DialRule::exact("announcement:menu", "*7").with_tenant("acme.test"));
+                // *9 is picked up elsewhere but free in acme.test
+                inv.rule(DialRule::exact("announcement:promo", "*91").with_tenant("acme.test"));
+            }))
+            .with_source(fixture("directory", |inv| {
+                inv.known(EntityKind::Extension, Some("acme.test"), "*781");
+                inv.known(EntityKind::Extension, Some("acme.test"), "*78");
+            }))
+            .lint()
+            .await;
+
+        assert_eq!(
+            problems(&report),
+            vec![(
+                LintProblem::Overlap,
+                LintSeverity::Error,
+                vec!["feature_code:dnd.on".to_string()],
+            )]
+        );
+        assert!(report.findings[0].message.contains("extension *78@acme.test"));
+
+        // A feature code prefix catching a later announcement's number
+        let report = RoutingLinter::new()
+            .with_source(fixture("feature_code", |inv| {
+                inv.rule(DialRule::exact("feature_code:dnd.on", "*78"));
+            }))
+            .with_source(fixture("announcement", |inv| {
+                inv.rule(DialRule::prefix("announcement:star", "*7"));
+            }))
+            .lint()
+            .await;
+        assert_eq!(
+            problems(&report),
+            vec![(
+                LintProblem::Overlap,
+                LintSeverity::Error,
+                vec!["feature_code:dnd.on".to_string(), "announcement:star".to_string()],
+            )]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_references_are_checked_only_against_reported_entities() {
+        let report = RoutingLinter::new()
+            .with_source(fixture("announcement", |inv| {
+                inv.rule(DialRule::exact("announcement:hours", "500").with_tenant("acme.test"));
+                inv.reference("announcement:hours", EntityKind::AudioFile, Some("acme.test"), "closed");
+                inv.reference("announcement:hours", EntityKind::Extension, Some("acme.test"), "2001");
+                inv.reference("announcement:hours", EntityKind::Extension, Some("carrier.test"), "bob");
+                inv.forward("announcement:hours", "500@acme.test", "500@acme.test");
+            }))
+            .lint()
+            .await;
+        assert_eq!(
+            problems(&report),
+            vec![(
+                LintProblem::Loop,
+                LintSeverity::Error,
+                vec!["announcement:hours".to_string()],
+            )]
+        );
+
+        let report = RoutingLinter::new()
+            .with_source(fixture("announcement", |inv| {
+                inv.reference("announcement:hours", EntityKind::AudioFile, Some("acme.test"), "closed");
+                inv.reference("announcement:hours", EntityKind::Extension, Some("acme.test"), "2001");
+                inv.reference("announcement:hours", EntityKind::Extension, Some("carrier.test"), "bob");
+            }))
+            .with_source(fixture("directory", |inv| {
+                inv.known(EntityKind::AudioFile, None, "closed");
+                inv.known(EntityKind::Extension, Some("acme.test"), "2002");
+            }))
+            .lint()
+            .await;
+        assert_eq!(report.findings.len(), 1);
+        assert_eq!(report.findings[0].message, "extension 2001 in acme.test does not exist");
+        assert_eq!(report.sources, vec!["announcement", "directory"]);
+    }
+}
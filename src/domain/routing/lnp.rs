@@ -0,0 +1,48 @@
+//! Number portability lookups
+//!
+//! In markets with number portability the dialed number does not tell
+//! which carrier serves it: a lookup ("LNP dip") returns the routing number
+//! of the serving carrier, or a trunk to send the call through. Routing
+//! applies the [`RoutingOverride`] before choosing where the call goes.
+
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+
+/// Where a ported number is really routed
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RoutingOverride {
+    /// Location routing number of the serving carrier (E.164), sent as the
+    /// `rn` parameter of the Request-URI
+    pub routing_number: Option<String>,
+    /// Trunk to route the call through
+    pub trunk: Option<String>,
+}
+
+impl RoutingOverride {
+    /// The number is routed as dialed
+    pub fn none() -> Self {
+        Self::default()
+    }
+
+    pub fn is_none(&self) -> bool {
+        self.routing_number.is_none() && self.trunk.is_none()
+    }
+}
+
+/// Source of routing overrides
+#[async_trait]
+pub trait RoutingLookup: Send + Sync {
+    /// Override of the E.164 `number`; [`RoutingOverride::none`] when it is
+    /// not ported
+    async fn lookup(&self, number: &str) -> Result<RoutingOverride, String>;
+}
+
+/// Lookup of deployments without number portability: nothing is ported
+pub struct NoopRoutingLookup;
+
+#[async_trait]
+impl RoutingLookup for NoopRoutingLookup {
+    async fn lookup(&self, _number: &str) -> Result<RoutingOverride, String> {
+        Ok(RoutingOverride::none())
+    }
+}
@@ -1,4 +1,24 @@
 //! Routing bounded context - manages call routing and dial plans
 
-// Placeholder for routing context
-// TODO: Implement full routing logic
+pub mod announcement;
+pub mod auto_attendant;
+pub mod lint;
+pub mod lnp;
+pub mod simulation;
+
+pub use announcement::{
+    AnnouncementError, AnnouncementRoute, AnnouncementRouteRepository, AnnouncementService,
+    FollowUp, MessageVariant, PlannedAnnouncement, TimedMessage,
+};
+pub use auto_attendant::{
+    AttendantAction, AttendantNumber, AttendantOption, AutoAttendant, AutoAttendantError,
+    AutoAttendantRepository, AutoAttendantService, PlannedAttendant,
+};
+pub use lint::{
+    internal_target, DialPattern, DialRule, EntityKind, Lint, LintFinding, LintProblem, LintReport,
+    LintSeverity, RoutingDirectory, RoutingInventory, RoutingLinter, FEATURE_CODE_STEP,
+};
+pub use lnp::{NoopRoutingLookup, RoutingLookup, RoutingOverride};
+pub use simulation::{
+    Explain, Explanation, RouteOutcome, RouteTrace, RoutedCall, RoutingSimulator, TraceStep,
+};
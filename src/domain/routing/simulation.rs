@@ -0,0 +1,310 @@
+//! Routing simulation - what the routing chain does with a call, without
+//! placing it
+//!
+//! Each routing component explains its decision for a [`RoutedCall`]
+//! through [`Explain`], using the same lookups the live INVITE path runs.
+//! [`RoutingSimulator`] runs the components in the order of the live path
+//! and records a [`TraceStep`] for every component that matched, until one
+//! ends the call's routing.
+
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use std::net::IpAddr;
+use std::sync::Arc;
+
+/// A call being routed
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct RoutedCall {
+    pub caller_uri: String,
+    /// Request URI, as rewritten by the steps so far
+    pub target_uri: String,
+    /// When the call is placed
+    pub at: DateTime<Utc>,
+    /// Address the INVITE comes from, for trunk maintenance
+    pub source: Option<IpAddr>,
+    /// Trunk selected so far
+    pub trunk: Option<String>,
+}
+
+impl RoutedCall {
+    pub fn new(caller_uri: String, target_uri: String, at: DateTime<Utc>) -> Self {
+        Self {
+            caller_uri,
+            target_uri,
+            at,
+            source: None,
+            trunk: None,
+        }
+    }
+
+    /// Dialed user part of the request URI
+    pub fn dialed(&self) -> &str {
+        uri_user(&self.target_uri)
+    }
+
+    /// Caller's user part
+    pub fn caller(&self) -> &str {
+        uri_user(&self.caller_uri)
+    }
+}
+
+/// How routing of a call ends
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum RouteOutcome {
+    /// A PBX feature runs for the dialed code
+    Feature { feature: String },
+    /// The PBX answers the call itself (test service, announcement,
+    /// auto-attendant)
+    Answered { by: String },
+    /// The call is refused
+    Rejected { status: u16, reason: String },
+    /// The callee's registered devices ring
+    Ring { contacts: Vec<String> },
+    /// The INVITE is forwarded to `target`
+    Forward { target: String, trunk: Option<String> },
+}
+
+/// What one routing component does with a call
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Explanation {
+    /// Rule that matched
+    pub rule: String,
+    /// New request URI, when the component rewrites it
+    pub target: Option<String>,
+    /// Trunk the component selects
+    pub trunk: Option<String>,
+    /// Set when the component ends routing
+    pub outcome: Option<RouteOutcome>,
+}
+
+impl Explanation {
+    pub fn matched(rule: impl Into<String>) -> Self {
+        Self {
+            rule: rule.into(),
+            target: None,
+            trunk: None,
+            outcome: None,
+        }
+    }
+
+    pub fn with_target(mut self, target: String) -> Self {
+        self.target = Some(target);
+        self
+    }
+
+    pub fn with_trunk(mut self, trunk: String) -> Self {
+        self.trunk = Some(trunk);
+        self
+    }
+
+    pub fn ending(mut self, outcome: RouteOutcome) -> Self {
+        self.outcome = Some(outcome);
+        self
+    }
+}
+
+/// A routing component explaining its decision
+///
+/// Implementations must not change any state: no call is created, no
+/// counter moves and no feature runs.
+#[async_trait]
+pub trait Explain: Send + Sync {
+    /// Name of the step in traces
+    fn step(&self) -> &'static str;
+
+    /// What the component does with `call`, `None` when it passes the call
+    /// on unchanged
+    async fn explain(&self, call: &RoutedCall) -> Option<Explanation>;
+}
+
+/// One step of a trace
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct TraceStep {
+    pub step: String,
+    pub rule: String,
+    /// `before -> after` when the step rewrote the request URI
+    pub transformation: Option<String>,
+    pub trunk: Option<String>,
+    pub outcome: Option<RouteOutcome>,
+}
+
+/// Ordered trace of a simulated call
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct RouteTrace {
+    pub steps: Vec<TraceStep>,
+    /// Request URI after every step
+    pub target_uri: String,
+    pub outcome: RouteOutcome,
+}
+
+/// Runs the routing chain for a call without placing it
+#[derive(Default)]
+pub struct RoutingSimulator {
+    steps: Vec<Arc<dyn Explain>>,
+}
+
+impl RoutingSimulator {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Append a step; steps run in the order added
+    pub fn with_step(mut self, step: Arc<dyn Explain>) -> Self {
+        self.steps.push(step);
+        self
+    }
+
+    /// Names of the steps, in order
+    pub fn steps(&self) -> Vec<&'static str> {
+        self.steps.iter().map(|step| step.step()).collect()
+    }
+
+    /// Trace of `call` through the chain
+    ///
+    /// A call no step ends is forwarded when a trunk was selected, and
+    /// refused with 404 otherwise, as the live path does for callees that
+    /// are not registered.
+    pub async fn simulate(&self, mut call: RoutedCall) -> RouteTrace {
+        let mut trace = Vec::new();
+        for step in &self.steps {
+            let Some(explanation) = step.explain(&call).await else {
+                continue;
+            };
+            let transformation = explanation
+                .target
+                .as_ref()
+                .filter(|target| **target != call.target_uri)
+                .map(|target| format!("{} -> {}", call.target_uri, target));
+            if let Some(target) = explanation.target {
+                call.target_uri = target;
+            }
+            if let Some(trunk) = &explanation.trunk {
+                call.trunk = Some(trunk.clone());
+            }
+            trace.push(TraceStep {
+                step: step.step().to_string(),
+                rule: explanation.rule,
+                transformation,
+                trunk: explanation.trunk,
+                outcome: explanation.outcome.clone(),
+            });
+            if let Some(outcome) = explanation.outcome {
+                return RouteTrace {
+                    steps: trace,
+                    target_uri: call.target_uri,
+                    outcome,
+                };
+            }
+        }
+
+        let outcome = match &call.trunk {
+            Some(trunk) => RouteOutcome::Forward {
+                target: call.target_uri.clone(),
+                trunk: Some(trunk.clone()),
+            },
+            None => RouteOutcome::Rejected {
+                status: 404,
+                reason: "callee not registered".to_string(),
+            },
+        };
+        RouteTrace {
+            steps: trace,
+            target_uri: call.target_uri,
+            outcome,
+        }
+    }
+}
+
+/// User part of a SIP URI (the whole URI when it has none)
+fn uri_user(uri: &str) -> &str {
+    let uri = uri.trim_start_matches("sips:").trim_start_matches("sip:");
+    uri.split('@').next().unwrap_or(uri)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct Rewrite(&'static str, &'static str);
+
+    #[async_trait]
+    impl Explain for Rewrite {
+        fn step(&self) -> &'static str {
+            "rewrite"
+        }
+
+        async fn explain(&self, call: &RoutedCall) -> Option<Explanation> {
+            (call.dialed() == self.0).then(|| {
+                Explanation::matched(format!("{} is {}", self.0, self.1))
+                    .with_target(format!("sip:{}@example.com", self.1))
+            })
+        }
+    }
+
+    struct Refuse;
+
+    #[async_trait]
+    impl Explain for Refuse {
+        fn step(&self) -> &'static str {
+            "refuse"
+        }
+
+        async fn explain(&self, _call: &RoutedCall) -> Option<Explanation> {
+            Some(Explanation::matched("everything").ending(RouteOutcome::Rejected {
+                status: 403,
+                reason: "refused".to_string(),
+            }))
+        }
+    }
+
+    fn call(dialed: &str) -> RoutedCall {
+        RoutedCall::new(
+            "sip:2001@example.com".to_string(),
+            format!("sip:{}@example.com", dialed),
+            Utc::now(),
+        )
+    }
+
+    #[tokio::test]
+    async fn test_steps_run_in_order_until_one_ends_routing() {
+        let simulator = RoutingSimulator::new()
+            .with_step(Arc::new(Rewrite("100", "200")))
+            .with_step(Arc::new(Rewrite("200", "300")))
+            .with_step(Arc::new(Refuse))
+            .with_step(Arc::new(Rewrite("300", "400")));
+        assert_eq!(simulator.steps(), vec!["rewrite", "rewrite", "refuse", "rewrite"]);
+
+        let trace = simulator.simulate(call("100")).await;
+        let transformations: Vec<_> = trace
+            .steps
+            .iter()
+            .map(|step| step.transformation.as_deref())
+            .collect();
+        assert_eq!(
+            transformations,
+            vec![
+                Some("sip:100@example.com -> sip:200@example.com"),
+                Some("sip:200@example.com -> sip:300@example.com"),
+                None,
+            ]
+        );
+        assert_eq!(trace.target_uri, "sip:300@example.com");
+        assert!(matches!(trace.outcome, RouteOutcome::Rejected { status: 403, .. }));
+    }
+
+    #[tokio::test]
+    async fn test_unrouted_call_is_not_found() {
+        let simulator = RoutingSimulator::new().with_step(Arc::new(Rewrite("100", "200")));
+        let trace = simulator.simulate(call("555")).await;
+        assert!(trace.steps.is_empty());
+        assert_eq!(
+            trace.outcome,
+            RouteOutcome::Rejected {
+                status: 404,
+                reason: "callee not registered".to_string()
+            }
+        );
+    }
+}
@@ -0,0 +1,507 @@
+//! Scheduled calls
+//!
+//! Wake-up calls and reminders: at a set local time the PBX calls an
+//! extension, plays an announcement (or runs an IVR flow) and, when the
+//! schedule asks for it, waits for a confirmation digit. Busy, unanswered
+//! and unconfirmed calls are retried as the retry policy allows; a schedule
+//! that runs out of attempts is failed. Times are kept local to the zone
+//! the schedule was made in, so a daily 06:30 call stays at 06:30 across
+//! DST changes.
+
+use crate::domain::campaign::{CampaignContent, DialResult, RetryPolicy};
+use crate::domain::shared::time_zone::{local_instant, parse_time_zone};
+use async_trait::async_trait;
+use chrono::{DateTime, Datelike, Duration, NaiveDateTime, Utc, Weekday};
+use chrono_tz::Tz;
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+use uuid::Uuid;
+
+#[derive(Debug, Error, PartialEq)]
+pub enum ScheduledCallError {
+    #[error("Scheduled call {0} not found")]
+    NotFound(Uuid),
+    #[error("Invalid scheduled call: {0}")]
+    Invalid(String),
+    #[error("Scheduled call is {0}")]
+    InvalidState(ScheduledCallStatus),
+    #[error("Storage error: {0}")]
+    Repository(String),
+}
+
+/// How a schedule repeats
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Recurrence {
+    Daily,
+    /// Monday to Friday
+    Weekdays,
+    Weekly,
+}
+
+impl Recurrence {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Daily => "daily",
+            Self::Weekdays => "weekdays",
+            Self::Weekly => "weekly",
+        }
+    }
+
+    pub fn parse(value: &str) -> Option<Self> {
+        match value {
+            "daily" => Some(Self::Daily),
+            "weekdays" => Some(Self::Weekdays),
+            "weekly" => Some(Self::Weekly),
+            _ => None,
+        }
+    }
+
+    /// Local time of the occurrence after `local`
+    pub fn next(&self, local: NaiveDateTime) -> NaiveDateTime {
+        match self {
+            Self::Daily => local + Duration::days(1),
+            Self::Weekly => local + Duration::weeks(1),
+            Self::Weekdays => {
+                let mut next = local + Duration::days(1);
+                while matches!(next.weekday(), Weekday::Sat | Weekday::Sun) {
+                    next += Duration::days(1);
+                }
+                next
+            }
+        }
+    }
+
+    /// Whether `local` is a day the schedule runs on
+    fn runs_on(&self, local: NaiveDateTime) -> bool {
+        *self != Self::Weekdays || !matches!(local.weekday(), Weekday::Sat | Weekday::Sun)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ScheduledCallStatus {
+    /// Waiting for its next call; recurring schedules stay pending
+    Pending,
+    /// Answered
+    Completed,
+    /// Answered and the confirmation digit pressed
+    Confirmed,
+    /// Out of attempts, or missed
+    Failed,
+    Cancelled,
+}
+
+impl ScheduledCallStatus {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Pending => "pending",
+            Self::Completed => "completed",
+            Self::Confirmed => "confirmed",
+            Self::Failed => "failed",
+            Self::Cancelled => "cancelled",
+        }
+    }
+
+    pub fn parse(value: &str) -> Option<Self> {
+        match value {
+            "pending" => Some(Self::Pending),
+            "completed" => Some(Self::Completed),
+            "confirmed" => Some(Self::Confirmed),
+            "failed" => Some(Self::Failed),
+            "cancelled" => Some(Self::Cancelled),
+            _ => None,
+        }
+    }
+}
+
+impl std::fmt::Display for ScheduledCallStatus {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// A scheduled call as it is created or changed
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct CreateScheduledCall {
+    /// Extension or SIP URI called
+    #[serde(default)]
+    pub target: String,
+    /// Local time of the (first) call, e.g. `2024-03-04T06:30:00`
+    pub time: NaiveDateTime,
+    /// Zone of `time`; the tenant's zone without
+    #[serde(default)]
+    pub time_zone: Option<String>,
+    #[serde(default)]
+    pub recurrence: Option<Recurrence>,
+    /// What the answered call hears; the configured wake-up prompt without
+    #[serde(default)]
+    pub content: Option<CampaignContent>,
+    /// Digit that confirms the call was heard
+    #[serde(default)]
+    pub confirm_digit: Option<char>,
+    #[serde(default)]
+    pub retry: Option<RetryPolicy>,
+    /// Emailed when the call fails
+    #[serde(default)]
+    pub notify_email: Option<String>,
+}
+
+impl CreateScheduledCall {
+    pub fn new(target: &str, time: NaiveDateTime) -> Self {
+        Self {
+            target: target.to_string(),
+            time,
+            time_zone: None,
+            recurrence: None,
+            content: None,
+            confirm_digit: None,
+            retry: None,
+            notify_email: None,
+        }
+    }
+}
+
+/// A call to place at a local time, and how its last one went
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ScheduledCall {
+    pub id: Uuid,
+    pub target: String,
+    /// SIP realm of the tenant the schedule belongs to
+    pub realm: Option<String>,
+    /// Local time of the current occurrence
+    pub local_time: NaiveDateTime,
+    pub time_zone: String,
+    pub recurrence: Option<Recurrence>,
+    pub content: CampaignContent,
+    pub confirm_digit: Option<char>,
+    pub retry: RetryPolicy,
+    pub notify_email: Option<String>,
+    /// Username, or `api` for schedules made through the admin API
+    pub created_by: String,
+    pub status: ScheduledCallStatus,
+    /// When the next attempt is due, retries included
+    pub next_attempt_at: DateTime<Utc>,
+    /// Attempts of the current occurrence
+    pub attempts: u32,
+    /// Result of the last attempt, e.g. `No answer`
+    pub last_result: Option<String>,
+    /// Outcome of the last finished occurrence
+    pub last_outcome: Option<ScheduledCallStatus>,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+}
+
+impl ScheduledCall {
+    /// Schedule from a validated request: the first occurrence at or after
+    /// `now`
+    pub fn new(
+        request: CreateScheduledCall,
+        realm: Option<String>,
+        time_zone: String,
+        created_by: &str,
+        content: CampaignContent,
+        retry: RetryPolicy,
+        now: DateTime<Utc>,
+    ) -> Result<Self, ScheduledCallError> {
+        let mut call = Self {
+            id: Uuid::new_v4(),
+            target: request.target.trim().to_string(),
+            realm,
+            local_time: request.time,
+            time_zone,
+            recurrence: request.recurrence,
+            content,
+            confirm_digit: request.confirm_digit,
+            retry,
+            notify_email: request.notify_email,
+            created_by: created_by.to_string(),
+            status: ScheduledCallStatus::Pending,
+            next_attempt_at: now,
+            attempts: 0,
+            last_result: None,
+            last_outcome: None,
+            created_at: now,
+            updated_at: now,
+        };
+        call.validate().map_err(ScheduledCallError::Invalid)?;
+        call.schedule_from(now)?;
+        Ok(call)
+    }
+
+    pub fn validate(&self) -> Result<(), String> {
+        if self.target.is_empty() {
+            return Err("target must not be empty".to_string());
+        }
+        parse_time_zone(&self.time_zone)?;
+        match &self.content {
+            CampaignContent::Announcement { prompts } if prompts.is_empty() => {
+                return Err("announcement must have at least one prompt".to_string())
+            }
+            CampaignContent::IvrFlow { flow_id } if flow_id.trim().is_empty() => {
+                return Err("flow_id must not be empty".to_string())
+            }
+            _ => {}
+        }
+        if let Some(digit) = self.confirm_digit {
+            if !matches!(digit, '0'..='9' | '*' | '#') {
+                return Err(format!("confirm_digit '{}' is not a DTMF digit", digit));
+            }
+        }
+        if self.retry.max_attempts == 0 {
+            return Err("retry.max_attempts must be at least 1".to_string());
+        }
+        if let Some(email) = &self.notify_email {
+            if !email.contains('@') {
+                return Err(format!("notify_email '{}' is not an email address", email));
+            }
+        }
+        Ok(())
+    }
+
+    fn zone(&self) -> Tz {
+        parse_time_zone(&self.time_zone).unwrap_or(Tz::UTC)
+    }
+
+    /// Move the current occurrence to the first one at or after `now`
+    ///
+    /// A one-off call in the past is refused.
+    fn schedule_from(&mut self, now: DateTime<Utc>) -> Result<(), ScheduledCallError> {
+        if let Some(recurrence) = self.recurrence {
+            while !recurrence.runs_on(self.local_time) || local_instant(self.zone(), self.local_time) < now {
+                self.local_time = recurrence.next(self.local_time);
+            }
+        }
+        self.next_attempt_at = local_instant(self.zone(), self.local_time);
+        if self.next_attempt_at < now {
+            return Err(ScheduledCallError::Invalid(format!(
+                "{} {} is in the past",
+                self.local_time, self.time_zone
+            )));
+        }
+        Ok(())
+    }
+
+    /// Change the time, repetition and content of a pending schedule
+    pub fn reschedule(
+        &mut self,
+        request: CreateScheduledCall,
+        time_zone: String,
+        content: CampaignContent,
+        retry: RetryPolicy,
+        now: DateTime<Utc>,
+    ) -> Result<(), ScheduledCallError> {
+        if self.status != ScheduledCallStatus::Pending {
+            return Err(ScheduledCallError::InvalidState(self.status));
+        }
+        let mut changed = self.clone();
+        changed.target = request.target.trim().to_string();
+        changed.local_time = request.time;
+        changed.time_zone = time_zone;
+        changed.recurrence = request.recurrence;
+        changed.content = content;
+        changed.confirm_digit = request.confirm_digit;
+        changed.retry = retry;
+        changed.notify_email = request.notify_email;
+        changed.attempts = 0;
+        changed.validate().map_err(ScheduledCallError::Invalid)?;
+        changed.schedule_from(now)?;
+        changed.updated_at = now;
+        *self = changed;
+        Ok(())
+    }
+
+    pub fn cancel(&mut self, now: DateTime<Utc>) -> Result<(), ScheduledCallError> {
+        if self.status != ScheduledCallStatus::Pending {
+            return Err(ScheduledCallError::InvalidState(self.status));
+        }
+        self.status = ScheduledCallStatus::Cancelled;
+        self.updated_at = now;
+        Ok(())
+    }
+
+    pub fn is_due(&self, now: DateTime<Utc>) -> bool {
+        self.status == ScheduledCallStatus::Pending && self.next_attempt_at <= now
+    }
+
+    /// Call-ID of the next attempt
+    pub fn next_call_id(&self) -> String {
+        format!("scheduled-{}-{}", self.id, self.attempts + 1)
+    }
+
+    /// Record what came of an attempt; returns the outcome once the
+    /// occurrence is over
+    pub fn finish_attempt(&mut self, result: &DialResult, now: DateTime<Utc>) -> Option<ScheduledCallStatus> {
+        self.attempts += 1;
+        let (outcome, cause, retry) = if result.is_answered() {
+            match self.confirm_digit {
+                Some(digit) if result.digits.contains(digit) => {
+                    (ScheduledCallStatus::Confirmed, "Confirmed".to_string(), false)
+                }
+                Some(_) => (ScheduledCallStatus::Failed, "Not confirmed".to_string(), true),
+                None => (ScheduledCallStatus::Completed, "Answered".to_string(), false),
+            }
+        } else if result.is_busy() {
+            (ScheduledCallStatus::Failed, "Busy".to_string(), self.retry.retry_busy)
+        } else if result.is_no_answer() {
+            (ScheduledCallStatus::Failed, "No answer".to_string(), self.retry.retry_no_answer)
+        } else {
+            let cause = match (&result.cause, result.status) {
+                (Some(cause), _) => cause.clone(),
+                (None, 0) => "Unreachable".to_string(),
+                (None, status) => format!("Rejected with {}", status),
+            };
+            (ScheduledCallStatus::Failed, cause, false)
+        };
+
+        self.last_result = Some(cause);
+        self.updated_at = now;
+        if retry && self.attempts < self.retry.max_attempts {
+            self.next_attempt_at = now + Duration::seconds(self.retry.retry_delay_secs as i64);
+            return None;
+        }
+        self.finish_occurrence(outcome, now);
+        Some(outcome)
+    }
+
+    /// Give up on an occurrence that was due long ago, e.g. while the PBX
+    /// was down
+    pub fn miss(&mut self, now: DateTime<Utc>) {
+        self.last_result = Some("Missed".to_string());
+        self.updated_at = now;
+        self.finish_occurrence(ScheduledCallStatus::Failed, now);
+    }
+
+    fn finish_occurrence(&mut self, outcome: ScheduledCallStatus, now: DateTime<Utc>) {
+        self.last_outcome = Some(outcome);
+        match self.recurrence {
+            Some(recurrence) => {
+                self.local_time = recurrence.next(self.local_time);
+                self.attempts = 0;
+                // Always in the future, recurring schedules cannot fail here
+                let _ = self.schedule_from(now);
+            }
+            None => self.status = outcome,
+        }
+    }
+}
+
+/// Scheduled call persistence
+#[async_trait]
+pub trait ScheduledCallRepository: Send + Sync {
+    async fn create(&self, call: &ScheduledCall) -> Result<(), String>;
+
+    async fn get(&self, id: Uuid) -> Result<Option<ScheduledCall>, String>;
+
+    /// Every schedule, soonest first
+    async fn list(&self) -> Result<Vec<ScheduledCall>, String>;
+
+    /// Pending schedules due at `now`, soonest first
+    async fn list_due(&self, now: DateTime<Utc>) -> Result<Vec<ScheduledCall>, String>;
+
+    /// Returns false when the schedule does not exist
+    async fn update(&self, call: &ScheduledCall) -> Result<bool, String>;
+
+    /// Returns false when the schedule does not exist
+    async fn delete(&self, id: Uuid) -> Result<bool, String>;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::{NaiveDate, TimeZone};
+
+    fn local(value: &str) -> NaiveDateTime {
+        NaiveDateTime::parse_from_str(value, "%Y-%m-%d %H:%M").unwrap()
+    }
+
+    fn wake_up(time: &str, zone: &str, recurrence: Option<Recurrence>, now: DateTime<Utc>) -> ScheduledCall {
+        let mut request = CreateScheduledCall::new("1001", local(time));
+        request.recurrence = recurrence;
+        request.confirm_digit = Some('1');
+        ScheduledCall::new(
+            request,
+            Some("hotel.example.com".to_string()),
+            zone.to_string(),
+            "1001",
+            CampaignContent::Announcement {
+                prompts: vec!["wakeup-call".to_string()],
+            },
+            RetryPolicy {
+                max_attempts: 2,
+                retry_delay_secs: 60,
+                ..Default::default()
+            },
+            now,
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn test_local_time_in_zone_and_past_refused() {
+        // Friday 2024-03-08 12:00 UTC
+        let now = Utc.with_ymd_and_hms(2024, 3, 8, 12, 0, 0).unwrap();
+        let call = wake_up("2024-03-09 06:30", "Europe/Berlin", None, now);
+        assert_eq!(call.next_attempt_at, Utc.with_ymd_and_hms(2024, 3, 9, 5, 30, 0).unwrap());
+
+        let request = CreateScheduledCall::new("1001", local("2024-03-08 06:30"));
+        let err = ScheduledCall::new(
+            request,
+            None,
+            "UTC".to_string(),
+            "1001",
+            CampaignContent::Announcement {
+                prompts: vec!["wakeup-call".to_string()],
+            },
+            RetryPolicy::default(),
+            now,
+        )
+        .unwrap_err();
+        assert!(matches!(err, ScheduledCallError::Invalid(_)));
+
+        // Weekdays skip to Monday
+        let call = wake_up("2024-03-08 06:30", "UTC", Some(Recurrence::Weekdays), now);
+        assert_eq!(call.local_time.date(), NaiveDate::from_ymd_opt(2024, 3, 11).unwrap());
+    }
+
+    #[test]
+    fn test_retries_then_fails() {
+        let now = Utc.with_ymd_and_hms(2024, 3, 8, 12, 0, 0).unwrap();
+        let mut call = wake_up("2024-03-09 06:30", "UTC", None, now);
+        let due = call.next_attempt_at;
+
+        assert_eq!(call.finish_attempt(&DialResult::rejected(480), due), None);
+        assert_eq!(call.next_attempt_at, due + Duration::seconds(60));
+        assert_eq!(call.last_result.as_deref(), Some("No answer"));
+        assert!(!call.is_due(due));
+
+        // Answered without the digit counts as a failed attempt
+        let outcome = call.finish_attempt(&DialResult::answered(), due + Duration::seconds(60));
+        assert_eq!(outcome, Some(ScheduledCallStatus::Failed));
+        assert_eq!(call.status, ScheduledCallStatus::Failed);
+        assert_eq!(call.last_result.as_deref(), Some("Not confirmed"));
+        assert_eq!(call.attempts, 2);
+    }
+
+    #[test]
+    fn test_recurring_call_moves_to_next_day_across_dst() {
+        // Saturday before DST starts in Berlin
+        let now = Utc.with_ymd_and_hms(2024, 3, 30, 0, 0, 0).unwrap();
+        let mut call = wake_up("2024-03-30 06:30", "Europe/Berlin", Some(Recurrence::Daily), now);
+        assert_eq!(call.next_attempt_at, Utc.with_ymd_and_hms(2024, 3, 30, 5, 30, 0).unwrap());
+
+        let outcome = call.finish_attempt(&DialResult::answered().with_digits("1"), call.next_attempt_at);
+        assert_eq!(outcome, Some(ScheduledCallStatus::Confirmed));
+        assert_eq!(call.status, ScheduledCallStatus::Pending);
+        assert_eq!(call.last_outcome, Some(ScheduledCallStatus::Confirmed));
+        // Still 06:30 local, now UTC+2
+        assert_eq!(call.next_attempt_at, Utc.with_ymd_and_hms(2024, 3, 31, 4, 30, 0).unwrap());
+        assert_eq!(call.attempts, 0);
+
+        call.cancel(now).unwrap();
+        assert_eq!(
+            call.cancel(now),
+            Err(ScheduledCallError::InvalidState(ScheduledCallStatus::Cancelled))
+        );
+    }
+}
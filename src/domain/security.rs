@@ -347,6 +347,13 @@ pub enum SecurityEvent {
         action: String,
         target: String,
     },
+    /// Access to personal data, e.g. a voicemail message
+    DataAccess {
+        username: String,
+        ip: String,
+        resource: String,
+        action: String,
+    },
 }
 
 /// Security audit log entry
@@ -464,7 +471,8 @@ impl SecurityAuditLogger {
                 | SecurityEvent::Logout { username: u, .. }
                 | SecurityEvent::PasswordChange { username: u, .. }
                 | SecurityEvent::AccountLockout { username: u, .. }
-                | SecurityEvent::PermissionDenied { username: u, .. } => u == username,
+                | SecurityEvent::PermissionDenied { username: u, .. }
+                | SecurityEvent::DataAccess { username: u, .. } => u == username,
                 _ => false,
             })
             .cloned()
@@ -484,7 +492,8 @@ impl SecurityAuditLogger {
                 | SecurityEvent::PermissionDenied { ip: i, .. }
                 | SecurityEvent::PolicyViolation { ip: i, .. }
                 | SecurityEvent::SuspiciousActivity { ip: i, .. }
-                | SecurityEvent::AdminAction { ip: i, .. } => i == ip,
+                | SecurityEvent::AdminAction { ip: i, .. }
+                | SecurityEvent::DataAccess { ip: i, .. } => i == ip,
             })
             .cloned()
             .collect()
@@ -3,8 +3,14 @@
 pub mod error;
 pub mod events;
 pub mod result;
+pub mod secret;
+pub mod sort;
+pub mod time_zone;
 pub mod value_objects;
 
 pub use error::DomainError;
 pub use result::Result;
+pub use secret::SecretBox;
+pub use sort::SortOrder;
+pub use time_zone::TimeZoneConfig;
 pub use value_objects::*;
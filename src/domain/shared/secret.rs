@@ -0,0 +1,66 @@
+//! Secret values that never print
+//!
+//! [`SecretBox`] holds a password, token or key in the clear for the code
+//! that needs it, but its `Debug` output and serialized form are a fixed
+//! placeholder, so a secret cannot reach logs, API responses or exported
+//! configuration by accident. Reading the value takes an explicit
+//! [`SecretBox::expose`].
+
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::fmt;
+
+/// Shown instead of a secret
+pub const SECRET_PLACEHOLDER: &str = "********";
+
+/// A secret value
+#[derive(Clone, PartialEq, Eq)]
+pub struct SecretBox(String);
+
+impl SecretBox {
+    pub fn new(secret: impl Into<String>) -> Self {
+        Self(secret.into())
+    }
+
+    /// The secret itself
+    pub fn expose(&self) -> &str {
+        &self.0
+    }
+
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+}
+
+impl From<String> for SecretBox {
+    fn from(secret: String) -> Self {
+        Self(secret)
+    }
+}
+
+impl From<&str> for SecretBox {
+    fn from(secret: &str) -> Self {
+        Self(secret.to_string())
+    }
+}
+
+impl fmt::Debug for SecretBox {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "SecretBox({})", SECRET_PLACEHOLDER)
+    }
+}
+
+impl Serialize for SecretBox {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(SECRET_PLACEHOLDER)
+    }
+}
+
+impl<'de> Deserialize<'de> for SecretBox {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        String::deserialize(deserializer).map(Self)
+    }
+}
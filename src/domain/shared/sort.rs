@@ -0,0 +1,38 @@
+//! Ordering of list queries
+
+use serde::{Deserialize, Serialize};
+
+/// Field and direction a list is ordered by
+///
+/// Fields are named as in the API; repositories map them to columns and
+/// fall back to their default order for fields they do not know.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SortOrder {
+    pub field: String,
+    pub descending: bool,
+}
+
+impl SortOrder {
+    pub fn ascending(field: &str) -> Self {
+        Self {
+            field: field.to_string(),
+            descending: false,
+        }
+    }
+
+    pub fn descending(field: &str) -> Self {
+        Self {
+            field: field.to_string(),
+            descending: true,
+        }
+    }
+
+    /// SQL direction keyword
+    pub fn direction(&self) -> &'static str {
+        if self.descending {
+            "DESC"
+        } else {
+            "ASC"
+        }
+    }
+}
@@ -0,0 +1,232 @@
+//! Time zones of users and tenants
+//!
+//! Timestamps are stored in UTC. A time zone (an IANA name such as
+//! `Europe/Berlin`) only decides how they are shown and where local days
+//! start: date-only filters and per-day buckets follow the zone's calendar,
+//! so a day on which daylight saving time starts or ends is 23 or 25 hours
+//! long.
+
+use chrono::{DateTime, Duration, FixedOffset, LocalResult, NaiveDate, NaiveDateTime, TimeZone, Utc};
+use chrono_tz::Tz;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+/// Default time zones
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct TimeZoneConfig {
+    /// Zone of tenants without their own
+    pub default_zone: String,
+    /// Per-tenant zones, keyed by SIP realm
+    pub tenants: HashMap<String, String>,
+}
+
+impl Default for TimeZoneConfig {
+    fn default() -> Self {
+        Self {
+            default_zone: "UTC".to_string(),
+            tenants: HashMap::new(),
+        }
+    }
+}
+
+impl TimeZoneConfig {
+    /// Names that are not IANA time zones, with the setting they are in
+    pub fn validate(&self) -> Result<(), String> {
+        parse_time_zone(&self.default_zone).map_err(|e| format!("default_zone: {}", e))?;
+        for (realm, zone) in &self.tenants {
+            parse_time_zone(zone).map_err(|e| format!("tenants.{}: {}", realm, e))?;
+        }
+        Ok(())
+    }
+
+    /// Zone of a user: their own, else their tenant's, else the default
+    ///
+    /// Invalid names are skipped.
+    pub fn zone_for(&self, realm: Option<&str>, user_zone: Option<&str>) -> Tz {
+        user_zone
+            .and_then(|zone| parse_time_zone(zone).ok())
+            .or_else(|| {
+                realm
+                    .and_then(|realm| self.tenants.get(realm))
+                    .and_then(|zone| parse_time_zone(zone).ok())
+            })
+            .unwrap_or_else(|| self.default_tz())
+    }
+
+    /// The default zone (UTC when its name is invalid)
+    pub fn default_tz(&self) -> Tz {
+        parse_time_zone(&self.default_zone).unwrap_or(Tz::UTC)
+    }
+}
+
+/// Parse an IANA time zone name
+pub fn parse_time_zone(name: &str) -> Result<Tz, String> {
+    name.trim()
+        .parse()
+        .map_err(|_| format!("Unknown time zone '{}'", name))
+}
+
+/// First instant of `date` in `zone`
+///
+/// Where midnight is skipped by a DST change, the day starts when the
+/// clocks are set forward.
+pub fn local_day_start(zone: Tz, date: NaiveDate) -> DateTime<Utc> {
+    local_instant(zone, date.and_hms_opt(0, 0, 0).unwrap())
+}
+
+/// Instant of the local time `local` in `zone`
+///
+/// A time repeated when the clocks are set back is its first occurrence; a
+/// time skipped when they are set forward is moved past the gap.
+pub fn local_instant(zone: Tz, local: NaiveDateTime) -> DateTime<Utc> {
+    let mut shifted = local;
+    // DST gaps are at most a few hours long
+    for _ in 0..24 * 4 {
+        match zone.from_local_datetime(&shifted) {
+            LocalResult::Single(time) | LocalResult::Ambiguous(time, _) => {
+                return time.with_timezone(&Utc)
+            }
+            LocalResult::None => shifted += Duration::minutes(15),
+        }
+    }
+    local.and_utc()
+}
+
+/// Parse a time filter: RFC 3339, or a date meaning the start of that day
+/// in `zone`
+///
+/// With `end_of_day` a date means the start of the next day, so that
+/// `to=2024-03-01` includes all of March 1st.
+pub fn parse_time_bound(value: &str, zone: Tz, end_of_day: bool) -> Result<DateTime<Utc>, String> {
+    let value = value.trim();
+    if let Ok(time) = DateTime::parse_from_rfc3339(value) {
+        return Ok(time.with_timezone(&Utc));
+    }
+    let date = NaiveDate::parse_from_str(value, "%Y-%m-%d").map_err(|_| {
+        format!(
+            "'{}' is neither an RFC 3339 time nor a date (YYYY-MM-DD)",
+            value
+        )
+    })?;
+    let date = if end_of_day {
+        date.succ_opt()
+            .ok_or_else(|| format!("Date {} is out of range", value))?
+    } else {
+        date
+    };
+    Ok(local_day_start(zone, date))
+}
+
+/// `time` with the offset `zone` has at that instant
+pub fn in_zone(time: DateTime<Utc>, zone: Tz) -> DateTime<FixedOffset> {
+    time.with_timezone(&zone).fixed_offset()
+}
+
+/// One local day of a report
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DayBucket {
+    pub date: NaiveDate,
+    /// `[start, end)` in UTC, clipped to the report window
+    pub start: DateTime<Utc>,
+    pub end: DateTime<Utc>,
+}
+
+/// The local days of `zone` overlapping `[from, to)`
+pub fn day_buckets(zone: Tz, from: DateTime<Utc>, to: DateTime<Utc>) -> Vec<DayBucket> {
+    let mut buckets = Vec::new();
+    let mut date = from.with_timezone(&zone).date_naive();
+    while let Some(next) = date.succ_opt() {
+        let start = local_day_start(zone, date).max(from);
+        if start >= to {
+            break;
+        }
+        let end = local_day_start(zone, next).min(to);
+        buckets.push(DayBucket { date, start, end });
+        date = next;
+    }
+    buckets
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn berlin() -> Tz {
+        parse_time_zone("Europe/Berlin").unwrap()
+    }
+
+    fn date(value: &str) -> NaiveDate {
+        NaiveDate::parse_from_str(value, "%Y-%m-%d").unwrap()
+    }
+
+    #[test]
+    fn test_dst_days_are_23_and_25_hours() {
+        let zone = berlin();
+        let from = parse_time_bound("2024-03-30", zone, false).unwrap();
+        let to = parse_time_bound("2024-04-01", zone, true).unwrap();
+        let hours: Vec<(NaiveDate, i64)> = day_buckets(zone, from, to)
+            .iter()
+            .map(|b| (b.date, (b.end - b.start).num_hours()))
+            .collect();
+        assert_eq!(
+            hours,
+            vec![
+                (date("2024-03-30"), 24),
+                (date("2024-03-31"), 23),
+                (date("2024-04-01"), 24),
+            ]
+        );
+
+        let from = parse_time_bound("2024-10-27", zone, false).unwrap();
+        let to = parse_time_bound("2024-10-27", zone, true).unwrap();
+        let buckets = day_buckets(zone, from, to);
+        assert_eq!(buckets.len(), 1);
+        assert_eq!((buckets[0].end - buckets[0].start).num_hours(), 25);
+    }
+
+    #[test]
+    fn test_time_bounds() {
+        let zone = berlin();
+        // Midnight in the zone, not in UTC
+        assert_eq!(
+            parse_time_bound("2024-03-01", zone, false).unwrap(),
+            Utc.with_ymd_and_hms(2024, 2, 29, 23, 0, 0).unwrap()
+        );
+        assert_eq!(
+            parse_time_bound("2024-07-01", zone, true).unwrap(),
+            Utc.with_ymd_and_hms(2024, 7, 1, 22, 0, 0).unwrap()
+        );
+        // Explicit times keep their offset
+        assert_eq!(
+            parse_time_bound("2024-03-01T08:00:00-05:00", zone, false).unwrap(),
+            Utc.with_ymd_and_hms(2024, 3, 1, 13, 0, 0).unwrap()
+        );
+        assert!(parse_time_bound("yesterday", zone, false).is_err());
+
+        // Midnight does not exist on the day Sao Paulo moved to DST in 2018
+        let zone = parse_time_zone("America/Sao_Paulo").unwrap();
+        assert_eq!(
+            local_day_start(zone, date("2018-11-04")),
+            Utc.with_ymd_and_hms(2018, 11, 4, 3, 0, 0).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_zone_precedence() {
+        let mut config = TimeZoneConfig::default();
+        config
+            .tenants
+            .insert("acme.example.com".to_string(), "America/New_York".to_string());
+
+        let zone = |realm, user| config.zone_for(realm, user).name().to_string();
+        assert_eq!(zone(Some("acme.example.com"), Some("Asia/Tokyo")), "Asia/Tokyo");
+        assert_eq!(zone(Some("acme.example.com"), None), "America/New_York");
+        assert_eq!(zone(Some("acme.example.com"), Some("Mars/Olympus")), "America/New_York");
+        assert_eq!(zone(Some("example.com"), None), "UTC");
+
+        assert!(config.validate().is_ok());
+        config.default_zone = "Europe/Nowhere".to_string();
+        assert!(config.validate().is_err());
+    }
+}
@@ -1,7 +1,9 @@
 //! Shared value objects used across multiple bounded contexts
 
 use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
 use std::fmt;
+use std::ops::RangeInclusive;
 use uuid::Uuid;
 
 /// Call identifier
@@ -94,48 +96,165 @@ impl fmt::Display for EndpointId {
     }
 }
 
-/// SIP URI value object
-#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+/// SIP or SIPS URI (RFC 3261 19.1)
+///
+/// User, password and parameters are kept unescaped. Equality and hashing
+/// use the [canonical form](SipUri::canonical); [`SipUri::equivalent`]
+/// applies the comparison rules of RFC 3261 19.1.4.
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct SipUri {
+    #[serde(default)]
+    secure: bool,
     user: String,
+    #[serde(default)]
+    password: Option<String>,
     host: String,
     port: Option<u16>,
+    /// URI parameters in order; flags such as `lr` have no value
+    #[serde(default)]
+    params: Vec<(String, Option<String>)>,
+    /// Header components after `?`
+    #[serde(default)]
+    headers: Vec<(String, String)>,
 }
 
+/// Parameters a URI never matches another without (RFC 3261 19.1.4)
+const SIGNIFICANT_PARAMS: [&str; 5] = ["transport", "user", "ttl", "method", "maddr"];
+
+/// Characters left unescaped besides alphanumerics, per URI component
+const UNRESERVED: &str = "-_.!~*'()";
+const USER_UNRESERVED: &str = "&=+$,;?/";
+const PASSWORD_UNRESERVED: &str = "&=+$,";
+const PARAM_UNRESERVED: &str = "[]/:&+$";
+const HEADER_UNRESERVED: &str = "[]/?:+$";
+
 impl SipUri {
     pub fn new(user: String, host: String, port: Option<u16>) -> Self {
-        Self { user, host, port }
+        Self {
+            secure: false,
+            user,
+            password: None,
+            host,
+            port,
+            params: Vec::new(),
+            headers: Vec::new(),
+        }
     }
 
+    /// Parse a `sip:` or `sips:` URI (not a name-addr with `<>`)
     pub fn parse(uri: &str) -> Result<Self, String> {
-        // Simple parsing for now, can be enhanced
-        if !uri.starts_with("sip:") {
-            return Err("URI must start with 'sip:'".to_string());
-        }
+        let uri = uri.trim();
+        let (secure, rest) = match uri.split_once(':') {
+            Some((scheme, rest)) if scheme.eq_ignore_ascii_case("sip") => (false, rest),
+            Some((scheme, rest)) if scheme.eq_ignore_ascii_case("sips") => (true, rest),
+            _ => return Err("URI must start with 'sip:' or 'sips:'".to_string()),
+        };
 
-        let uri = &uri[4..]; // Remove "sip:" prefix
-        let parts: Vec<&str> = uri.split('@').collect();
+        // '@' is escaped everywhere but between user and host
+        let (userinfo, rest) = match rest.rsplit_once('@') {
+            Some((userinfo, rest)) => (Some(userinfo), rest),
+            None => (None, rest),
+        };
+        let (rest, headers) = match rest.split_once('?') {
+            Some((rest, headers)) => (rest, headers),
+            None => (rest, ""),
+        };
+        let (host_port, params) = match rest.split_once(';') {
+            Some((host_port, params)) => (host_port, params),
+            None => (rest, ""),
+        };
 
-        if parts.len() != 2 {
-            return Err("Invalid SIP URI format".to_string());
+        let (host, port) = match host_port.strip_prefix('[') {
+            Some(v6) => {
+                let (address, after) = v6
+                    .split_once(']')
+                    .ok_or_else(|| format!("Unterminated IPv6 reference in '{}'", uri))?;
+                let port = match after {
+                    "" => None,
+                    after => Some(
+                        after
+                            .strip_prefix(':')
+                            .ok_or_else(|| format!("Invalid host in '{}'", uri))?,
+                    ),
+                };
+                (format!("[{}]", address), port)
+            }
+            None => match host_port.rsplit_once(':') {
+                Some((host, port)) => (host.to_string(), Some(port)),
+                None => (host_port.to_string(), None),
+            },
+        };
+        if host.is_empty() {
+            return Err(format!("Missing host in '{}'", uri));
         }
+        let port = port
+            .map(|port| port.parse::<u16>().map_err(|_| format!("Invalid port '{}'", port)))
+            .transpose()?;
 
-        let user = parts[0].to_string();
-        let host_port: Vec<&str> = parts[1].split(':').collect();
-        let host = host_port[0].to_string();
-        let port = if host_port.len() > 1 {
-            host_port[1].parse().ok()
-        } else {
-            None
+        let (user, password) = match userinfo {
+            Some(userinfo) => {
+                let (user, password) = match userinfo.split_once(':') {
+                    Some((user, password)) => (user, Some(unescape(password))),
+                    None => (userinfo, None),
+                };
+                if user.is_empty() {
+                    return Err(format!("Empty user in '{}'", uri));
+                }
+                (unescape(user), password)
+            }
+            None => (String::new(), None),
         };
 
-        Ok(Self { user, host, port })
+        let params = params
+            .split(';')
+            .filter(|param| !param.is_empty())
+            .map(|param| match param.split_once('=') {
+                Some((name, value)) => (unescape(name), Some(unescape(value))),
+                None => (unescape(param), None),
+            })
+            .collect();
+        let headers = headers
+            .split('&')
+            .filter(|header| !header.is_empty())
+            .map(|header| {
+                let (name, value) = header.split_once('=').unwrap_or((header, ""));
+                (unescape(name), unescape(value))
+            })
+            .collect();
+
+        Ok(Self {
+            secure,
+            user,
+            password,
+            host,
+            port,
+            params,
+            headers,
+        })
+    }
+
+    /// `sip` or `sips`
+    pub fn scheme(&self) -> &'static str {
+        if self.secure {
+            "sips"
+        } else {
+            "sip"
+        }
+    }
+
+    pub fn is_secure(&self) -> bool {
+        self.secure
     }
 
+    /// User part, unescaped; empty for a URI of a host
     pub fn user(&self) -> &str {
         &self.user
     }
 
+    pub fn password(&self) -> Option<&str> {
+        self.password.as_deref()
+    }
+
     pub fn host(&self) -> &str {
         &self.host
     }
@@ -143,16 +262,561 @@ impl SipUri {
     pub fn port(&self) -> Option<u16> {
         self.port
     }
+
+    pub fn params(&self) -> &[(String, Option<String>)] {
+        &self.params
+    }
+
+    /// Whether the URI has parameter `name` (case-insensitive)
+    pub fn has_param(&self, name: &str) -> bool {
+        self.params.iter().any(|(n, _)| n.eq_ignore_ascii_case(name))
+    }
+
+    /// Value of parameter `name` (case-insensitive); None for a flag
+    pub fn param(&self, name: &str) -> Option<&str> {
+        self.params
+            .iter()
+            .find(|(n, _)| n.eq_ignore_ascii_case(name))
+            .and_then(|(_, value)| value.as_deref())
+    }
+
+    pub fn headers(&self) -> &[(String, String)] {
+        &self.headers
+    }
+
+    /// The URI with another user part
+    pub fn with_user(mut self, user: &str) -> Self {
+        self.user = user.to_string();
+        self
+    }
+
+    /// The URI with parameter `name` set (replacing any existing value)
+    pub fn with_param(mut self, name: &str, value: Option<&str>) -> Self {
+        self.params.retain(|(n, _)| !n.eq_ignore_ascii_case(name));
+        self.params
+            .push((name.to_string(), value.map(str::to_string)));
+        self
+    }
+
+    /// Address of record: scheme, user, host and port, the host in lower
+    /// case (RFC 3261 10.3)
+    pub fn address_of_record(&self) -> SipUri {
+        Self {
+            secure: self.secure,
+            user: self.user.clone(),
+            password: None,
+            host: self.host.to_ascii_lowercase(),
+            port: self.port,
+            params: Vec::new(),
+            headers: Vec::new(),
+        }
+    }
+
+    /// The URI with host and parameters in lower case (but for `method`),
+    /// and parameters and headers sorted by name
+    fn normalized(&self) -> SipUri {
+        let mut params: Vec<(String, Option<String>)> = self
+            .params
+            .iter()
+            .map(|(name, value)| {
+                let name = name.to_ascii_lowercase();
+                let value = match value {
+                    Some(value) if name != "method" => Some(value.to_ascii_lowercase()),
+                    value => value.clone(),
+                };
+                (name, value)
+            })
+            .collect();
+        params.sort();
+        let mut headers: Vec<(String, String)> = self
+            .headers
+            .iter()
+            .map(|(name, value)| (name.to_ascii_lowercase(), value.clone()))
+            .collect();
+        headers.sort();
+        Self {
+            host: self.host.to_ascii_lowercase(),
+            params,
+            headers,
+            ..self.clone()
+        }
+    }
+
+    /// Canonical string form: escaping made uniform, host and parameters
+    /// in lower case, parameters and headers sorted
+    pub fn canonical(&self) -> String {
+        self.normalized().to_string()
+    }
+
+    /// Whether two URIs are equivalent (RFC 3261 19.1.4)
+    ///
+    /// User and password are compared case-sensitively, the host is not.
+    /// A parameter present in both must match; `transport`, `user`, `ttl`,
+    /// `method` and `maddr` must be in both or neither, other parameters in
+    /// only one are ignored. Headers must all match.
+    pub fn equivalent(&self, other: &SipUri) -> bool {
+        if self.secure != other.secure
+            || self.user != other.user
+            || self.password != other.password
+            || !self.host.eq_ignore_ascii_case(&other.host)
+            || self.port != other.port
+        {
+            return false;
+        }
+
+        let (ours, theirs) = (self.normalized(), other.normalized());
+        let one_sided = |a: &SipUri, b: &SipUri| {
+            a.params.iter().any(|(name, value)| {
+                match b.params.iter().find(|(other, _)| other == name) {
+                    Some((_, other_value)) => other_value != value,
+                    None => SIGNIFICANT_PARAMS.contains(&name.as_str()),
+                }
+            })
+        };
+        !one_sided(&ours, &theirs) && !one_sided(&theirs, &ours) && ours.headers == theirs.headers
+    }
+}
+
+impl PartialEq for SipUri {
+    fn eq(&self, other: &Self) -> bool {
+        self.canonical() == other.canonical()
+    }
+}
+
+impl Eq for SipUri {}
+
+impl std::hash::Hash for SipUri {
+    fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
+        self.canonical().hash(state);
+    }
+}
+
+impl std::str::FromStr for SipUri {
+    type Err = String;
+
+    fn from_str(uri: &str) -> Result<Self, Self::Err> {
+        Self::parse(uri)
+    }
 }
 
 impl fmt::Display for SipUri {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:", self.scheme())?;
+        if !self.user.is_empty() {
+            f.write_str(&escape(&self.user, USER_UNRESERVED))?;
+            if let Some(password) = &self.password {
+                write!(f, ":{}", escape(password, PASSWORD_UNRESERVED))?;
+            }
+            f.write_str("@")?;
+        }
+        f.write_str(&self.host)?;
         if let Some(port) = self.port {
-            write!(f, "sip:{}@{}:{}", self.user, self.host, port)
+            write!(f, ":{}", port)?;
+        }
+        for (name, value) in &self.params {
+            write!(f, ";{}", escape(name, PARAM_UNRESERVED))?;
+            if let Some(value) = value {
+                write!(f, "={}", escape(value, PARAM_UNRESERVED))?;
+            }
+        }
+        for (i, (name, value)) in self.headers.iter().enumerate() {
+            let separator = if i == 0 { '?' } else { '&' };
+            write!(
+                f,
+                "{}{}={}",
+                separator,
+                escape(name, HEADER_UNRESERVED),
+                escape(value, HEADER_UNRESERVED)
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// Decode `%XX` escapes; malformed ones are kept as they are
+fn unescape(value: &str) -> String {
+    let bytes = value.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let escaped = (bytes[i] == b'%')
+            .then(|| value.get(i + 1..i + 3))
+            .flatten()
+            .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+        match escaped {
+            Some(byte) => {
+                decoded.push(byte);
+                i += 3;
+            }
+            None => {
+                decoded.push(bytes[i]);
+                i += 1;
+            }
+        }
+    }
+    String::from_utf8_lossy(&decoded).into_owned()
+}
+
+/// Escape every byte but alphanumerics, unreserved marks and `allowed`
+fn escape(value: &str, allowed: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for byte in value.bytes() {
+        let c = byte as char;
+        if c.is_ascii_alphanumeric() || UNRESERVED.contains(c) || allowed.contains(c) {
+            escaped.push(c);
+        } else {
+            escaped.push_str(&format!("%{:02X}", byte));
+        }
+    }
+    escaped
+}
+
+/// Dialing rules of a country, for parsing its phone numbers
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CountryRules {
+    /// Country calling code without `+` (e.g. "1", "44")
+    pub country_code: String,
+    /// Trunk prefix of national numbers ("0"; "1" in North America)
+    #[serde(default)]
+    pub national_prefix: String,
+    /// Prefixes dialed before international numbers (e.g. "00", "011")
+    #[serde(default)]
+    pub international_prefixes: Vec<String>,
+    /// Shortest national significant number, in digits
+    pub min_digits: usize,
+    /// Longest national significant number, in digits
+    pub max_digits: usize,
+}
+
+impl CountryRules {
+    pub fn new(
+        country_code: &str,
+        national_prefix: &str,
+        international_prefixes: &[&str],
+        digits: RangeInclusive<usize>,
+    ) -> Self {
+        Self {
+            country_code: country_code.to_string(),
+            national_prefix: national_prefix.to_string(),
+            international_prefixes: international_prefixes.iter().map(|p| p.to_string()).collect(),
+            min_digits: *digits.start(),
+            max_digits: *digits.end(),
+        }
+    }
+
+    /// `national` has the length of a national significant number
+    fn accepts(&self, national: &str) -> bool {
+        (self.min_digits..=self.max_digits).contains(&national.len())
+    }
+
+    /// National significant number of `digits` dialed within the country
+    fn national_number<'a>(&self, digits: &'a str) -> Option<&'a str> {
+        let without_prefix = digits
+            .strip_prefix(self.national_prefix.as_str())
+            .filter(|rest| !self.national_prefix.is_empty() && self.accepts(rest));
+        without_prefix
+            .or_else(|| self.accepts(digits).then_some(digits))
+            // Country code given without `+`
+            .or_else(|| {
+                digits
+                    .strip_prefix(self.country_code.as_str())
+                    .filter(|rest| self.accepts(rest))
+            })
+    }
+}
+
+/// Countries phone numbers are parsed for, and each tenant's home country
+///
+/// This is a small ruleset covering number lengths and dialing prefixes,
+/// not a full numbering database: numbers of countries not listed only
+/// parse in international form.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct NumberingPlan {
+    /// Home country code of tenants not listed in `tenants`
+    pub default_country: String,
+    pub countries: Vec<CountryRules>,
+    /// Home country code per tenant, keyed by realm
+    pub tenants: HashMap<String, String>,
+}
+
+impl Default for NumberingPlan {
+    fn default() -> Self {
+        Self {
+            default_country: "1".to_string(),
+            countries: vec![
+                CountryRules::new("1", "1", &["011"], 10..=10),
+                CountryRules::new("44", "0", &["00"], 9..=10),
+                CountryRules::new("49", "0", &["00"], 6..=12),
+                CountryRules::new("33", "0", &["00"], 9..=9),
+                CountryRules::new("61", "0", &["0011"], 9..=9),
+            ],
+            tenants: HashMap::new(),
+        }
+    }
+}
+
+impl NumberingPlan {
+    /// Rules of the country with `country_code`
+    pub fn country(&self, country_code: &str) -> Option<&CountryRules> {
+        self.countries.iter().find(|c| c.country_code == country_code)
+    }
+
+    /// Home country of `tenant` (a realm)
+    pub fn home_country(&self, tenant: Option<&str>) -> Option<&CountryRules> {
+        let code = tenant
+            .and_then(|tenant| self.tenants.get(tenant))
+            .unwrap_or(&self.default_country);
+        self.country(code)
+    }
+
+    /// E.164 form of `input`, when it is a phone number
+    pub fn normalize(&self, input: &str, tenant: Option<&str>) -> Option<String> {
+        PhoneNumber::parse(input, self, tenant).ok().map(|n| n.e164())
+    }
+
+    /// `a` and `b` are the same number, however each is presented
+    ///
+    /// Strings that are not phone numbers (extensions, numbers of
+    /// countries not in the plan) match when their digits are equal.
+    pub fn same_number(&self, a: &str, b: &str, tenant: Option<&str>) -> bool {
+        if a.eq_ignore_ascii_case(b) {
+            return true;
+        }
+        match (
+            PhoneNumber::parse(a, self, tenant),
+            PhoneNumber::parse(b, self, tenant),
+        ) {
+            (Ok(a), Ok(b)) => a.same_as(&b),
+            _ => match (Presented::of(a), Presented::of(b)) {
+                (Ok(a), Ok(b)) => a.digits == b.digits && a.extension == b.extension,
+                _ => false,
+            },
+        }
+    }
+}
+
+/// Why a string is not a phone number
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PhoneNumberError {
+    /// No digits
+    Empty,
+    /// Not a digit, `+` or visual separator
+    InvalidCharacter(char),
+    /// International number of a country not in the numbering plan
+    UnknownCountry(String),
+    /// No country accepts the number's length (e.g. an extension)
+    InvalidLength(String),
+}
+
+impl fmt::Display for PhoneNumberError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Empty => write!(f, "Phone number has no digits"),
+            Self::InvalidCharacter(c) => write!(f, "Invalid character '{}' in phone number", c),
+            Self::UnknownCountry(digits) => write!(f, "Unknown country of +{}", digits),
+            Self::InvalidLength(digits) => write!(f, "{} is not a complete phone number", digits),
+        }
+    }
+}
+
+impl std::error::Error for PhoneNumberError {}
+
+/// Phone number in canonical form
+///
+/// Parsed from what trunks and users present (`+14155551234`,
+/// `14155551234`, `(415) 555-1234`, `001-415-555-1234`, `tel:` and SIP
+/// URIs), using the home country of the tenant for numbers without a
+/// country code.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct PhoneNumber {
+    country_code: String,
+    /// National significant number, without trunk prefix
+    national: String,
+    extension: Option<String>,
+}
+
+impl PhoneNumber {
+    pub fn parse(
+        input: &str,
+        plan: &NumberingPlan,
+        tenant: Option<&str>,
+    ) -> Result<Self, PhoneNumberError> {
+        let presented = Presented::of(input)?;
+        let digits = presented.digits.as_str();
+        let home = plan.home_country(tenant);
+
+        let number = if presented.international {
+            Self::international(digits, plan)?
+        } else if let Some(rest) = home.and_then(|home| {
+            home.international_prefixes
+                .iter()
+                .find_map(|prefix| digits.strip_prefix(prefix.as_str()))
+        }) {
+            Self::international(rest, plan)?
+        } else if let Some((home, national)) =
+            home.and_then(|home| home.national_number(digits).map(|n| (home, n)))
+        {
+            Self {
+                country_code: home.country_code.clone(),
+                national: national.to_string(),
+                extension: None,
+            }
         } else {
-            write!(f, "sip:{}@{}", self.user, self.host)
+            // Dialed abroad with another country's international prefix
+            plan.countries
+                .iter()
+                .flat_map(|country| &country.international_prefixes)
+                .filter_map(|prefix| digits.strip_prefix(prefix.as_str()))
+                .find_map(|rest| Self::international(rest, plan).ok())
+                .ok_or_else(|| PhoneNumberError::InvalidLength(digits.to_string()))?
+        };
+
+        Ok(Self {
+            extension: presented.extension,
+            ..number
+        })
+    }
+
+    /// Number from its digits after the international prefix
+    fn international(digits: &str, plan: &NumberingPlan) -> Result<Self, PhoneNumberError> {
+        let country = plan
+            .countries
+            .iter()
+            .filter(|c| digits.starts_with(c.country_code.as_str()))
+            .max_by_key(|c| c.country_code.len())
+            .ok_or_else(|| PhoneNumberError::UnknownCountry(digits.to_string()))?;
+        let rest = &digits[country.country_code.len()..];
+
+        // Trunk prefix kept after the country code, as in +44 (0)20 ...
+        let national = if country.accepts(rest) {
+            rest
+        } else {
+            rest.strip_prefix(country.national_prefix.as_str())
+                .filter(|rest| !country.national_prefix.is_empty() && country.accepts(rest))
+                .ok_or_else(|| PhoneNumberError::InvalidLength(digits.to_string()))?
+        };
+
+        Ok(Self {
+            country_code: country.country_code.clone(),
+            national: national.to_string(),
+            extension: None,
+        })
+    }
+
+    pub fn country_code(&self) -> &str {
+        &self.country_code
+    }
+
+    /// National significant number, e.g. `4155551234`
+    pub fn national_number(&self) -> &str {
+        &self.national
+    }
+
+    /// Number as dialed within its country, with the trunk prefix
+    pub fn national(&self, plan: &NumberingPlan) -> String {
+        let prefix = plan
+            .country(&self.country_code)
+            .map(|c| c.national_prefix.as_str())
+            .unwrap_or_default();
+        format!("{}{}", prefix, self.national)
+    }
+
+    pub fn extension(&self) -> Option<&str> {
+        self.extension.as_deref()
+    }
+
+    /// E.164 form, e.g. `+14155551234`
+    pub fn e164(&self) -> String {
+        format!("+{}{}", self.country_code, self.national)
+    }
+
+    /// Same number; extensions only count when both have one
+    pub fn same_as(&self, other: &PhoneNumber) -> bool {
+        self.country_code == other.country_code
+            && self.national == other.national
+            && match (&self.extension, &other.extension) {
+                (Some(a), Some(b)) => a == b,
+                _ => true,
+            }
+    }
+}
+
+impl fmt::Display for PhoneNumber {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.extension {
+            Some(extension) => write!(f, "{};ext={}", self.e164(), extension),
+            None => write!(f, "{}", self.e164()),
+        }
+    }
+}
+
+/// Digits of a number as presented, separators removed
+struct Presented {
+    /// Written with a leading `+`
+    international: bool,
+    digits: String,
+    extension: Option<String>,
+}
+
+impl Presented {
+    fn of(input: &str) -> Result<Self, PhoneNumberError> {
+        let (number, extension) = split_extension(user_part(input));
+        let number = number.split(';').next().unwrap_or_default().trim();
+
+        let mut international = false;
+        let mut digits = String::new();
+        for c in number.chars() {
+            match c {
+                '0'..='9' => digits.push(c),
+                '+' if digits.is_empty() && !international => international = true,
+                ' ' | '-' | '.' | '(' | ')' | '/' => {}
+                _ => return Err(PhoneNumberError::InvalidCharacter(c)),
+            }
+        }
+        if digits.is_empty() {
+            return Err(PhoneNumberError::Empty);
+        }
+
+        Ok(Self {
+            international,
+            digits,
+            extension,
+        })
+    }
+}
+
+/// User part of a SIP or tel URI (or name-addr); other input as is
+fn user_part(input: &str) -> &str {
+    let input = match (input.find('<'), input.find('>')) {
+        (Some(start), Some(end)) if start < end => &input[start + 1..end],
+        _ => input,
+    };
+    let input = input.trim();
+    let without_scheme = ["sips:", "sip:", "tel:"]
+        .iter()
+        .find_map(|scheme| {
+            input
+                .get(..scheme.len())
+                .filter(|s| s.eq_ignore_ascii_case(scheme))
+                .map(|_| &input[scheme.len()..])
+        })
+        .unwrap_or(input);
+    without_scheme.split('@').next().unwrap_or_default()
+}
+
+/// Split off an extension (`;ext=12`, `ext. 12`, `x12`, `#12`)
+fn split_extension(number: &str) -> (&str, Option<String>) {
+    let lower = number.to_ascii_lowercase();
+    for marker in [";ext=", "ext.", "ext", "x", "#"] {
+        if let Some(i) = lower.rfind(marker) {
+            let extension = number[i + marker.len()..].trim();
+            if !extension.is_empty() && extension.chars().all(|c| c.is_ascii_digit()) {
+                return (&number[..i], Some(extension.to_string()));
+            }
         }
     }
+    (number, None)
 }
 
 #[cfg(test)]
@@ -180,4 +844,196 @@ mod tests {
         let uri_with_port = SipUri::new("bob".to_string(), "example.com".to_string(), Some(5060));
         assert_eq!(uri_with_port.to_string(), "sip:bob@example.com:5060");
     }
+
+    #[test]
+    fn test_sip_uri_parse_components() {
+        let uri =
+            SipUri::parse("SIPS:al%69ce:secret@[2001:db8::1]:5061;transport=TCP;lr?subject=hi%20there&priority=urgent")
+                .unwrap();
+        assert!(uri.is_secure());
+        assert_eq!(uri.user(), "alice");
+        assert_eq!(uri.password(), Some("secret"));
+        assert_eq!(uri.host(), "[2001:db8::1]");
+        assert_eq!(uri.port(), Some(5061));
+        assert_eq!(uri.param("transport"), Some("TCP"));
+        assert!(uri.has_param("lr"));
+        assert_eq!(uri.param("lr"), None);
+        assert_eq!(uri.headers()[0], ("subject".to_string(), "hi there".to_string()));
+        assert_eq!(
+            uri.to_string(),
+            "sips:alice:secret@[2001:db8::1]:5061;transport=TCP;lr?subject=hi%20there&priority=urgent"
+        );
+
+        let host_only = SipUri::parse("sip:example.com;transport=udp").unwrap();
+        assert_eq!(host_only.user(), "");
+        assert_eq!(host_only.to_string(), "sip:example.com;transport=udp");
+
+        assert!(SipUri::parse("tel:+15551234567").is_err());
+        assert!(SipUri::parse("sip:alice@").is_err());
+        assert!(SipUri::parse("sip:alice@example.com:port").is_err());
+    }
+
+    #[test]
+    fn test_sip_uri_canonical() {
+        let a = SipUri::parse("sip:alice@Example.COM;Transport=UDP;lr").unwrap();
+        let b = SipUri::parse("sip:alice@example.com;lr;transport=udp").unwrap();
+        assert_eq!(a.canonical(), "sip:alice@example.com;lr;transport=udp");
+        assert_eq!(a, b);
+        assert_ne!(a, SipUri::parse("sip:Alice@example.com;lr;transport=udp").unwrap());
+        assert_eq!(
+            a.address_of_record().to_string(),
+            "sip:alice@example.com"
+        );
+    }
+
+    #[test]
+    fn test_sip_uri_equivalence() {
+        let equivalent = [
+            ("sip:%61lice@atlanta.com;transport=TCP", "sip:alice@AtLanTa.CoM;Transport=tcp"),
+            ("sip:carol@chicago.com", "sip:carol@chicago.com;newparam=5"),
+            ("sip:carol@chicago.com;security=on", "sip:carol@chicago.com;newparam=5"),
+            (
+                "sip:biloxi.com;transport=tcp;method=REGISTER?to=sip:bob%40biloxi.com",
+                "sip:biloxi.com;method=REGISTER;transport=tcp?to=sip:bob%40biloxi.com",
+            ),
+            (
+                "sip:alice@atlanta.com?subject=project%20x&priority=urgent",
+                "sip:alice@atlanta.com?priority=urgent&subject=project%20x",
+            ),
+        ];
+        for (a, b) in equivalent {
+            let (a, b) = (SipUri::parse(a).unwrap(), SipUri::parse(b).unwrap());
+            assert!(a.equivalent(&b) && b.equivalent(&a), "{} ~ {}", a, b);
+        }
+
+        let different = [
+            ("SIP:ALICE@AtLanTa.CoM;Transport=udp", "sip:alice@AtLanTa.CoM;Transport=UDP"),
+            ("sip:bob@biloxi.com", "sip:bob@biloxi.com:5060"),
+            ("sip:bob@biloxi.com", "sip:bob@biloxi.com;transport=udp"),
+            ("sip:bob@biloxi.com", "sip:bob@biloxi.com:6000;transport=tcp"),
+            ("sip:carol@chicago.com", "sip:carol@chicago.com?Subject=next%20meeting"),
+            ("sip:bob@phone21.boxesbybob.com", "sip:bob@192.0.2.4"),
+        ];
+        for (a, b) in different {
+            let (a, b) = (SipUri::parse(a).unwrap(), SipUri::parse(b).unwrap());
+            assert!(!a.equivalent(&b) && !b.equivalent(&a), "{} !~ {}", a, b);
+        }
+    }
+
+    fn plan() -> NumberingPlan {
+        let mut plan = NumberingPlan::default();
+        plan.tenants.insert("uk.example.com".to_string(), "44".to_string());
+        plan
+    }
+
+    #[test]
+    fn test_phone_number_normalization() {
+        let plan = plan();
+        let us = [
+            "+14155551234",
+            "14155551234",
+            "4155551234",
+            "(415) 555-1234",
+            "+1 415.555.1234",
+            "001-415-555-1234",
+            "011 1 415 555 1234",
+            "sip:+14155551234@trunk.example.net",
+            "sip:4155551234@trunk.example.net;user=phone",
+            "\"Alice\" <sip:14155551234@trunk.example.net>;tag=1",
+            "tel:+1-415-555-1234",
+        ];
+        for input in us {
+            let number = PhoneNumber::parse(input, &plan, None).unwrap();
+            assert_eq!(number.e164(), "+14155551234", "{}", input);
+            assert_eq!(number.country_code(), "1");
+            assert_eq!(number.national_number(), "4155551234");
+            assert_eq!(number.national(&plan), "14155551234");
+        }
+
+        let uk = [
+            "+44 20 7946 0958",
+            "+44 (0)20 7946 0958",
+            "0044 20 7946 0958",
+            "020 7946 0958",
+            "442079460958",
+        ];
+        for input in uk {
+            let number = PhoneNumber::parse(input, &plan, Some("uk.example.com")).unwrap();
+            assert_eq!(number.e164(), "+442079460958", "{}", input);
+            assert_eq!(number.national(&plan), "02079460958");
+        }
+        // International form parses for every tenant
+        assert_eq!(
+            plan.normalize("+44 20 7946 0958", None).as_deref(),
+            Some("+442079460958")
+        );
+        // The tenant's home country applies to national numbers
+        assert_eq!(
+            plan.normalize("020 7946 0958", None),
+            None,
+            "not a North American number"
+        );
+    }
+
+    #[test]
+    fn test_phone_number_extensions() {
+        let plan = plan();
+        for input in [
+            "+1 415 555 1234 x12",
+            "+1 415 555 1234 ext. 12",
+            "tel:+14155551234;ext=12",
+            "4155551234#12",
+        ] {
+            let number = PhoneNumber::parse(input, &plan, None).unwrap();
+            assert_eq!(number.extension(), Some("12"), "{}", input);
+            assert_eq!(number.to_string(), "+14155551234;ext=12");
+        }
+        assert_eq!(
+            PhoneNumber::parse("+14155551234", &plan, None).unwrap().extension(),
+            None
+        );
+    }
+
+    #[test]
+    fn test_phone_number_errors() {
+        let plan = plan();
+        assert_eq!(PhoneNumber::parse("", &plan, None), Err(PhoneNumberError::Empty));
+        assert_eq!(
+            PhoneNumber::parse("sip:alice@example.com", &plan, None),
+            Err(PhoneNumberError::InvalidCharacter('a'))
+        );
+        assert_eq!(
+            PhoneNumber::parse("1001", &plan, None),
+            Err(PhoneNumberError::InvalidLength("1001".to_string()))
+        );
+        assert_eq!(
+            PhoneNumber::parse("+81 3 1234 5678", &plan, None),
+            Err(PhoneNumberError::UnknownCountry("81312345678".to_string()))
+        );
+        assert!(matches!(
+            PhoneNumber::parse("+1 415 555 123", &plan, None),
+            Err(PhoneNumberError::InvalidLength(_))
+        ));
+    }
+
+    #[test]
+    fn test_same_number() {
+        let plan = plan();
+        assert!(plan.same_number("+14155551234", "(415) 555-1234", None));
+        assert!(plan.same_number("sip:14155551234@trunk.example.net", "001-415-555-1234", None));
+        assert!(!plan.same_number("+14155551234", "+14155551235", None));
+        assert!(plan.same_number("020 7946 0958", "+442079460958", Some("uk.example.com")));
+        assert!(!plan.same_number("020 7946 0958", "+442079460958", None));
+
+        // Extensions only differ when both numbers have one
+        assert!(plan.same_number("+14155551234 x12", "4155551234", None));
+        assert!(!plan.same_number("+14155551234 x12", "+14155551234 x13", None));
+
+        // Extensions and unknown countries compare by digits
+        assert!(plan.same_number("1001", "10-01", None));
+        assert!(!plan.same_number("1001", "1002", None));
+        assert!(plan.same_number("+81 3 1234 5678", "+81312345678", None));
+        assert!(plan.same_number("Assistant", "assistant", None));
+        assert!(!plan.same_number("alice", "bob", None));
+    }
 }
@@ -1,4 +1,5 @@
 /// SIP Trunk configuration and management
+use crate::domain::shared::SecretBox;
 use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
 use std::net::SocketAddr;
@@ -49,7 +50,7 @@ pub struct SipTrunk {
 
     // Authentication
     pub username: Option<String>,
-    pub password: Option<String>,
+    pub password: Option<SecretBox>,
     pub auth_username: Option<String>,
     pub realm: Option<String>,
 
@@ -151,7 +152,7 @@ impl SipTrunk {
     /// Set authentication credentials
     pub fn with_credentials(mut self, username: String, password: String) -> Self {
         self.username = Some(username.clone());
-        self.password = Some(password);
+        self.password = Some(SecretBox::from(password));
         self.auth_username = Some(username);
         self
     }
@@ -0,0 +1,453 @@
+//! Speed dials / short codes
+//!
+//! Users keep personal speed dials (`*1` -> their manager's cell) and admins
+//! manage company-wide short codes that apply to everyone. A personal entry
+//! with the same code overrides the company one. Codes are resolved before
+//! the dialed number is routed.
+
+use crate::domain::feature_code::{standard_feature_codes, FeatureCode};
+use crate::domain::routing::lint::{internal_target, DialRule, EntityKind, Lint, RoutingInventory};
+use crate::domain::routing::simulation::{Explain, Explanation, RoutedCall};
+use crate::domain::shared::NumberingPlan;
+use crate::domain::user::UserRepository;
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::sync::Arc;
+use tracing::{debug, warn};
+use uuid::Uuid;
+
+/// Prefix of every speed dial code
+pub const SPEED_DIAL_PREFIX: char = '*';
+
+/// Maximum number of digits after the prefix
+pub const SPEED_DIAL_MAX_DIGITS: usize = 3;
+
+/// Speed dial entry
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SpeedDial {
+    pub id: Uuid,
+    /// Owning user; `None` for company-wide short codes
+    pub user_id: Option<i32>,
+    /// Dialed code, e.g. `*1`
+    pub code: String,
+    /// Destination number or SIP URI
+    pub target: String,
+    pub label: Option<String>,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+}
+
+impl SpeedDial {
+    pub fn new(user_id: Option<i32>, code: String, target: String, label: Option<String>) -> Self {
+        let now = Utc::now();
+        Self {
+            id: Uuid::new_v4(),
+            user_id,
+            code,
+            target,
+            label,
+            created_at: now,
+            updated_at: now,
+        }
+    }
+
+    /// Whether this is a company-wide short code
+    pub fn is_company_wide(&self) -> bool {
+        self.user_id.is_none()
+    }
+
+    /// Destination as a SIP URI, using `domain` for plain numbers
+    pub fn target_uri(&self, domain: &str) -> String {
+        if self.target.starts_with("sip:") || self.target.starts_with("sips:") {
+            self.target.clone()
+        } else {
+            format!("sip:{}@{}", self.target, domain)
+        }
+    }
+
+    /// Like `target_uri`, with number targets written in E.164 however
+    /// they were entered (`02079460018` -> `sip:+442079460018@...`);
+    /// extensions and other targets are used as entered
+    pub fn target_uri_normalized(&self, domain: &str, plan: &NumberingPlan) -> String {
+        if self.target.starts_with("sip:") || self.target.starts_with("sips:") {
+            return self.target.clone();
+        }
+        match plan.normalize(&self.target, Some(domain)) {
+            Some(number) => format!("sip:{}@{}", number, domain),
+            None => self.target_uri(domain),
+        }
+    }
+
+    /// Validate code and target
+    pub fn validate(&self) -> Result<(), SpeedDialError> {
+        validate_code(&self.code)?;
+        validate_target(&self.target)
+    }
+}
+
+/// Speed dial validation errors
+#[derive(Debug, Clone, PartialEq)]
+pub enum SpeedDialError {
+    /// Code does not match the speed dial pattern
+    InvalidCode(String),
+    /// Target is empty or not a number/SIP URI
+    InvalidTarget(String),
+    /// Code collides with a system feature code
+    ReservedCode { code: String, feature: String },
+    /// Code already defined in the same scope
+    Duplicate(String),
+}
+
+impl std::fmt::Display for SpeedDialError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SpeedDialError::InvalidCode(code) => write!(
+                f,
+                "Invalid speed dial code '{}': expected '{}' followed by 1-{} digits",
+                code, SPEED_DIAL_PREFIX, SPEED_DIAL_MAX_DIGITS
+            ),
+            SpeedDialError::InvalidTarget(target) => {
+                write!(f, "Invalid speed dial target '{}'", target)
+            }
+            SpeedDialError::ReservedCode { code, feature } => {
+                write!(f, "Code '{}' is reserved for {}", code, feature)
+            }
+            SpeedDialError::Duplicate(code) => write!(f, "Speed dial '{}' already exists", code),
+        }
+    }
+}
+
+impl std::error::Error for SpeedDialError {}
+
+/// Whether a dialed string has the shape of a speed dial code
+pub fn is_speed_dial_pattern(dialed: &str) -> bool {
+    match dialed.strip_prefix(SPEED_DIAL_PREFIX) {
+        Some(digits) => {
+            !digits.is_empty()
+                && digits.len() <= SPEED_DIAL_MAX_DIGITS
+                && digits.chars().all(|c| c.is_ascii_digit())
+        }
+        None => false,
+    }
+}
+
+/// Validate a speed dial code against the pattern and system feature codes
+pub fn validate_code(code: &str) -> Result<(), SpeedDialError> {
+    if !is_speed_dial_pattern(code) {
+        return Err(SpeedDialError::InvalidCode(code.to_string()));
+    }
+
+    let dialed = FeatureCode::exact("speed_dial", code, "Speed dial");
+    if let Some(feature) = standard_feature_codes()
+        .into_iter()
+        .find(|feature| feature.collides_with(&dialed))
+    {
+        return Err(SpeedDialError::ReservedCode {
+            code: code.to_string(),
+            feature: feature.description,
+        });
+    }
+
+    Ok(())
+}
+
+/// Validate a speed dial target (number or SIP URI, never another code)
+pub fn validate_target(target: &str) -> Result<(), SpeedDialError> {
+    let target = target.trim();
+    let valid = if let Some(uri) = target
+        .strip_prefix("sip:")
+        .or_else(|| target.strip_prefix("sips:"))
+    {
+        !uri.is_empty() && !uri.contains(char::is_whitespace)
+    } else {
+        let digits = target.strip_prefix('+').unwrap_or(target);
+        !digits.is_empty() && digits.chars().all(|c| c.is_ascii_digit())
+    };
+
+    if valid {
+        Ok(())
+    } else {
+        Err(SpeedDialError::InvalidTarget(target.to_string()))
+    }
+}
+
+/// Resolve a dialed code; personal entries win over company-wide ones
+pub fn resolve<'a>(
+    personal: &'a [SpeedDial],
+    company: &'a [SpeedDial],
+    dialed: &str,
+) -> Option<&'a SpeedDial> {
+    if !is_speed_dial_pattern(dialed) {
+        return None;
+    }
+    personal
+        .iter()
+        .find(|s| s.code == dialed)
+        .or_else(|| company.iter().find(|s| s.code == dialed))
+}
+
+/// Speed dials in effect for a user, sorted by code
+///
+/// Used for listing and for rendering speed dials onto phone keys.
+pub fn effective_speed_dials(personal: &[SpeedDial], company: &[SpeedDial]) -> Vec<SpeedDial> {
+    let mut merged: BTreeMap<&str, &SpeedDial> = BTreeMap::new();
+    for speed_dial in company.iter().chain(personal.iter()) {
+        merged.insert(&speed_dial.code, speed_dial);
+    }
+    merged.into_values().cloned().collect()
+}
+
+/// Speed dial repository trait
+#[cfg_attr(test, mockall::automock)]
+#[async_trait::async_trait]
+pub trait SpeedDialRepository: Send + Sync {
+    /// Create a speed dial
+    async fn create(&self, speed_dial: &SpeedDial) -> Result<(), String>;
+
+    /// Get speed dial by ID
+    async fn get_by_id(&self, id: Uuid) -> Result<Option<SpeedDial>, String>;
+
+    /// List personal speed dials of a user
+    async fn list_for_user(&self, user_id: i32) -> Result<Vec<SpeedDial>, String>;
+
+    /// List company-wide short codes
+    async fn list_company(&self) -> Result<Vec<SpeedDial>, String>;
+
+    /// Update code, target and label
+    async fn update(&self, speed_dial: &SpeedDial) -> Result<(), String>;
+
+    /// Delete speed dial
+    async fn delete(&self, id: Uuid) -> Result<(), String>;
+}
+
+/// Resolves dialed short codes for callers
+pub struct SpeedDialService {
+    repository: Arc<dyn SpeedDialRepository>,
+    users: Arc<dyn UserRepository>,
+    /// Normalizes number targets
+    numbering: Option<Arc<NumberingPlan>>,
+}
+
+impl SpeedDialService {
+    pub fn new(repository: Arc<dyn SpeedDialRepository>, users: Arc<dyn UserRepository>) -> Self {
+        Self {
+            repository,
+            users,
+            numbering: None,
+        }
+    }
+
+    /// Dial number targets in E.164, using the home country of the
+    /// caller's domain
+    pub fn with_numbering_plan(mut self, numbering: Arc<NumberingPlan>) -> Self {
+        self.numbering = Some(numbering);
+        self
+    }
+
+    /// Destination of a resolved speed dial as a SIP URI
+    pub fn target_uri(&self, speed_dial: &SpeedDial, domain: &str) -> String {
+        match &self.numbering {
+            Some(plan) => speed_dial.target_uri_normalized(domain, plan),
+            None => speed_dial.target_uri(domain),
+        }
+    }
+
+    /// Resolve `dialed` for the calling user, if it is a known code
+    pub async fn resolve(&self, caller_username: &str, dialed: &str) -> Option<SpeedDial> {
+        if !is_speed_dial_pattern(dialed) {
+            return None;
+        }
+
+        let personal = match self.users.find_by_username(caller_username).await {
+            Ok(Some(user)) => self
+                .repository
+                .list_for_user(user.id)
+                .await
+                .unwrap_or_else(|e| {
+                    warn!("Failed to load speed dials for {}: {}", caller_username, e);
+                    Vec::new()
+                }),
+            Ok(None) => Vec::new(),
+            Err(e) => {
+                warn!("Failed to look up caller {}: {}", caller_username, e);
+                Vec::new()
+            }
+        };
+        let company = self.repository.list_company().await.unwrap_or_else(|e| {
+            warn!("Failed to load company speed dials: {}", e);
+            Vec::new()
+        });
+
+        let resolved = resolve(&personal, &company, dialed).cloned();
+        if let Some(speed_dial) = &resolved {
+            debug!(
+                "Speed dial {} for {} -> {}",
+                dialed, caller_username, speed_dial.target
+            );
+        }
+        resolved
+    }
+
+    /// Speed dial a call from `caller_uri` to `to_uri` dials, with the
+    /// request URI it is rewritten to
+    pub async fn rewrite(&self, caller_uri: &str, to_uri: &str) -> Option<(SpeedDial, String)> {
+        let (dialed, domain) = to_uri
+            .trim_start_matches("sips:")
+            .trim_start_matches("sip:")
+            .split_once('@')?;
+        let caller = caller_uri
+            .trim_start_matches("sip:")
+            .trim_start_matches("sips:")
+            .split('@')
+            .next()?;
+        let speed_dial = self.resolve(caller, dialed).await?;
+        let target = self.target_uri(&speed_dial, domain);
+        Some((speed_dial, target))
+    }
+}
+
+#[async_trait]
+impl Explain for SpeedDialService {
+    fn step(&self) -> &'static str {
+        "speed_dial"
+    }
+
+    async fn explain(&self, call: &RoutedCall) -> Option<Explanation> {
+        let (speed_dial, target) = self.rewrite(&call.caller_uri, &call.target_uri).await?;
+        let owner = if speed_dial.is_company_wide() { "company" } else { "personal" };
+        Some(
+            Explanation::matched(format!(
+                "{} speed dial {} to {}",
+                owner, speed_dial.code, speed_dial.target
+            ))
+            .with_target(target),
+        )
+    }
+}
+
+#[async_trait]
+impl Lint for SpeedDialService {
+    fn source(&self) -> &'static str {
+        "speed_dial"
+    }
+
+    /// Company codes; they rewrite the dialed number and routing goes on
+    async fn inventory(&self, inventory: &mut RoutingInventory) {
+        let company = match self.repository.list_company().await {
+            Ok(company) => company,
+            Err(e) => {
+                warn!("Routing lint: failed to load company speed dials: {}", e);
+                return;
+            }
+        };
+        for speed_dial in company {
+            let id = format!("speed_dial:{}", speed_dial.id);
+            inventory.rule(DialRule::exact(id.clone(), &speed_dial.code).passing_on());
+            if let Some((user, domain)) = internal_target(&speed_dial.target, None) {
+                inventory.reference(&id, EntityKind::Extension, domain.as_deref(), &user);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn speed_dial(user_id: Option<i32>, code: &str, target: &str) -> SpeedDial {
+        SpeedDial::new(user_id, code.to_string(), target.to_string(), None)
+    }
+
+    #[test]
+    fn test_personal_overrides_company() {
+        let company = vec![
+            speed_dial(None, "*1", "1000"),
+            speed_dial(None, "*2", "sip:reception@example.com"),
+        ];
+        let personal = vec![speed_dial(Some(7), "*1", "+15551234567")];
+
+        assert_eq!(resolve(&personal, &company, "*1").unwrap().target, "+15551234567");
+        assert_eq!(
+            resolve(&personal, &company, "*2").unwrap().target,
+            "sip:reception@example.com"
+        );
+        assert!(resolve(&personal, &company, "*3").is_none());
+        assert!(resolve(&personal, &company, "1000").is_none());
+
+        let effective = effective_speed_dials(&personal, &company);
+        assert_eq!(effective.len(), 2);
+        assert_eq!(effective[0].user_id, Some(7));
+        assert!(effective[1].is_company_wide());
+    }
+
+    #[test]
+    fn test_feature_code_collisions() {
+        assert!(validate_code("*1").is_ok());
+        assert!(validate_code("*12").is_ok());
+        assert!(validate_code("*91").is_ok());
+
+        // Exact feature codes
+        assert!(matches!(validate_code("*9"), Err(SpeedDialError::ReservedCode { .. })));
+        assert!(matches!(validate_code("*10"), Err(SpeedDialError::ReservedCode { .. })));
+        assert!(matches!(validate_code("*78"), Err(SpeedDialError::ReservedCode { .. })));
+        assert!(matches!(validate_code("*97"), Err(SpeedDialError::ReservedCode { .. })));
+        // Codes swallowed by prefix features (*8<ext>, *11<ext>)
+        assert!(matches!(validate_code("*8"), Err(SpeedDialError::ReservedCode { .. })));
+        assert!(matches!(validate_code("*85"), Err(SpeedDialError::ReservedCode { .. })));
+        assert!(matches!(validate_code("*112"), Err(SpeedDialError::ReservedCode { .. })));
+
+        // Pattern
+        assert!(matches!(validate_code("1"), Err(SpeedDialError::InvalidCode(_))));
+        assert!(matches!(validate_code("*"), Err(SpeedDialError::InvalidCode(_))));
+        assert!(matches!(validate_code("*1234"), Err(SpeedDialError::InvalidCode(_))));
+        assert!(matches!(validate_code("*1a"), Err(SpeedDialError::InvalidCode(_))));
+    }
+
+    #[test]
+    fn test_normalized_target_uri() {
+        let mut plan = NumberingPlan::default();
+        plan.tenants
+            .insert("uk.example.com".to_string(), "44".to_string());
+
+        let cell = speed_dial(Some(1), "*1", "02079460018");
+        assert_eq!(
+            cell.target_uri_normalized("uk.example.com", &plan),
+            "sip:+442079460018@uk.example.com"
+        );
+        assert_eq!(
+            cell.target_uri("uk.example.com"),
+            "sip:02079460018@uk.example.com"
+        );
+
+        let us = speed_dial(Some(1), "*2", "4155551234");
+        assert_eq!(
+            us.target_uri_normalized("example.com", &plan),
+            "sip:+14155551234@example.com"
+        );
+
+        let extension = speed_dial(None, "*3", "1001");
+        assert_eq!(
+            extension.target_uri_normalized("example.com", &plan),
+            "sip:1001@example.com"
+        );
+
+        let uri = speed_dial(None, "*4", "sip:+14155551234@trunk.example.net");
+        assert_eq!(
+            uri.target_uri_normalized("example.com", &plan),
+            "sip:+14155551234@trunk.example.net"
+        );
+    }
+
+    #[test]
+    fn test_target_validation() {
+        assert!(validate_target("+15551234567").is_ok());
+        assert!(validate_target("sip:bob@example.com").is_ok());
+        assert!(validate_target("*1").is_err());
+        assert!(validate_target("").is_err());
+
+        let dial = speed_dial(Some(1), "*1", "1000");
+        assert_eq!(dial.target_uri("example.com"), "sip:1000@example.com");
+    }
+}
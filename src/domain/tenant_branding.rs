@@ -0,0 +1,454 @@
+//! Per-tenant branding of hold music, prompts and outbound caller identity
+//!
+//! Hosted tenants (keyed by SIP realm, like the fraud and hold policies and
+//! the audio library's tenant scope) may set their own default MOH file, a
+//! prompt language and prompt set, a display name template for outbound
+//! calls and a custom header added to requests toward their trunks.
+//! Anything a tenant leaves unset falls back to the global defaults.
+
+use crate::domain::audio::{AudioCategory, AudioLibrary, AudioReference, Language};
+use crate::domain::call_announcer::AnnouncementRequest;
+use serde::{Deserialize, Deserializer, Serialize};
+use std::collections::HashMap;
+use std::sync::{Arc, RwLock};
+use tracing::{info, warn};
+
+/// Reference kind recorded on audio files used by a tenant's branding
+const BRANDING_REFERENCE: &str = "branding";
+
+/// Header added to requests sent toward a tenant's trunks
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BrandingHeader {
+    pub name: String,
+    pub value: String,
+}
+
+/// Branding of one tenant
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct TenantBranding {
+    /// Audio library id of the default MOH file
+    pub moh_file: Option<String>,
+    /// MOH class played to the tenant's calls, ahead of `moh_file`
+    pub moh_class: Option<String>,
+    /// Language prompts are played in, e.g. "es"
+    pub prompt_language: Option<String>,
+    /// Prompt set; `<set>/<prompt>` is played when it exists
+    pub prompt_set: Option<String>,
+    /// From display name of outbound calls; `{user}` is replaced by the
+    /// caller's user part and `{display}` by the caller's own display name
+    pub display_name: Option<String>,
+    /// Header added to requests toward the tenant's trunks
+    pub header: Option<BrandingHeader>,
+}
+
+impl TenantBranding {
+    /// Check the fields that do not depend on stored audio
+    pub fn validate(&self) -> Result<(), String> {
+        if let Some(code) = &self.prompt_language {
+            if Language::from_code(code).is_none() {
+                return Err(format!("Unsupported prompt language: {}", code));
+            }
+        }
+        if let Some(set) = &self.prompt_set {
+            if set.is_empty()
+                || !set
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+            {
+                return Err(format!("Invalid prompt set: {}", set));
+            }
+        }
+        if let Some(template) = &self.display_name {
+            if template.chars().any(|c| c == '"' || c.is_control()) {
+                return Err(
+                    "Display name must not contain quotes or control characters".to_string()
+                );
+            }
+        }
+        if let Some(header) = &self.header {
+            let token = |c: char| c.is_ascii_alphanumeric() || "-.!%*_+`'~".contains(c);
+            if header.name.is_empty() || !header.name.chars().all(token) {
+                return Err(format!("Invalid header name: {}", header.name));
+            }
+            if header.value.chars().any(|c| c == '\r' || c == '\n') {
+                return Err("Header value must be a single line".to_string());
+            }
+        }
+        Ok(())
+    }
+
+    /// Language of the tenant's prompts
+    pub fn language(&self) -> Option<Language> {
+        self.prompt_language
+            .as_deref()
+            .and_then(Language::from_code)
+    }
+
+    /// Display name of an outbound call from `user` (whose own display name
+    /// is `display`), if the tenant sets one
+    pub fn display_name_for(&self, user: &str, display: Option<&str>) -> Option<String> {
+        self.display_name.as_ref().map(|template| {
+            template
+                .replace("{user}", user)
+                .replace("{display}", display.unwrap_or(user))
+        })
+    }
+}
+
+/// Partial update of a tenant's branding
+///
+/// Fields left out are kept; fields set to `null` are cleared.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct BrandingPatch {
+    #[serde(deserialize_with = "present")]
+    pub moh_file: Option<Option<String>>,
+    #[serde(deserialize_with = "present")]
+    pub moh_class: Option<Option<String>>,
+    #[serde(deserialize_with = "present")]
+    pub prompt_language: Option<Option<String>>,
+    #[serde(deserialize_with = "present")]
+    pub prompt_set: Option<Option<String>>,
+    #[serde(deserialize_with = "present")]
+    pub display_name: Option<Option<String>>,
+    #[serde(deserialize_with = "present")]
+    pub header: Option<Option<BrandingHeader>>,
+}
+
+/// Tell a field set to `null` (`Some(None)`) from a missing one (`None`)
+fn present<'de, D, T>(deserializer: D) -> Result<Option<Option<T>>, D::Error>
+where
+    D: Deserializer<'de>,
+    T: Deserialize<'de>,
+{
+    Option::<T>::deserialize(deserializer).map(Some)
+}
+
+impl BrandingPatch {
+    /// `branding` with this patch applied
+    pub fn apply(self, mut branding: TenantBranding) -> TenantBranding {
+        if let Some(moh_file) = self.moh_file {
+            branding.moh_file = moh_file;
+        }
+        if let Some(moh_class) = self.moh_class {
+            branding.moh_class = moh_class;
+        }
+        if let Some(prompt_language) = self.prompt_language {
+            branding.prompt_language = prompt_language;
+        }
+        if let Some(prompt_set) = self.prompt_set {
+            branding.prompt_set = prompt_set;
+        }
+        if let Some(display_name) = self.display_name {
+            branding.display_name = display_name;
+        }
+        if let Some(header) = self.header {
+            branding.header = header;
+        }
+        branding
+    }
+}
+
+/// Branding of every tenant, with the global fallbacks
+pub struct BrandingRegistry {
+    /// MOH source of calls whose tenant sets none
+    default_moh_source: String,
+    tenants: RwLock<HashMap<String, TenantBranding>>,
+    /// Resolves MOH file ids and checks that they exist
+    library: Option<Arc<AudioLibrary>>,
+}
+
+impl BrandingRegistry {
+    pub fn new(default_moh_source: String) -> Self {
+        Self {
+            default_moh_source,
+            tenants: RwLock::new(HashMap::new()),
+            library: None,
+        }
+    }
+
+    /// Look MOH files up in the audio library
+    pub fn with_audio_library(mut self, library: Arc<AudioLibrary>) -> Self {
+        self.library = Some(library);
+        self
+    }
+
+    /// Set a tenant's branding as configured, without checking its files
+    pub fn set_tenant_branding(&self, realm: &str, branding: TenantBranding) {
+        self.tenants
+            .write()
+            .unwrap()
+            .insert(realm.to_string(), branding);
+    }
+
+    /// Branding of a tenant, if it has any
+    pub fn get(&self, realm: &str) -> Option<TenantBranding> {
+        self.tenants.read().unwrap().get(realm).cloned()
+    }
+
+    /// Apply `patch` to a tenant's branding
+    ///
+    /// A MOH file must be a MOH upload of the tenant (or a global one) in
+    /// the audio library; the file is marked as referenced by the tenant.
+    pub fn update(&self, realm: &str, patch: BrandingPatch) -> Result<TenantBranding, String> {
+        let current = self.get(realm).unwrap_or_default();
+        let branding = patch.apply(current.clone());
+        branding.validate()?;
+
+        if branding.moh_file != current.moh_file {
+            if let Some(id) = &branding.moh_file {
+                let library = self
+                    .library
+                    .as_ref()
+                    .ok_or_else(|| "Audio library not available".to_string())?;
+                let owner = Self::moh_owner(library, realm, id)
+                    .ok_or_else(|| format!("MOH file {} not found", id))?;
+                library.add_reference(owner.as_deref(), id, Self::reference(realm));
+            }
+            if let (Some(id), Some(library)) = (&current.moh_file, &self.library) {
+                let owner = Self::moh_owner(library, realm, id).flatten();
+                library.remove_reference(owner.as_deref(), id, &Self::reference(realm));
+            }
+        }
+
+        self.set_tenant_branding(realm, branding.clone());
+        info!("Branding of tenant {} updated", realm);
+        Ok(branding)
+    }
+
+    fn reference(realm: &str) -> AudioReference {
+        AudioReference::new(BRANDING_REFERENCE, realm)
+    }
+
+    /// Scope (tenant or global) holding MOH file `id` as seen by `realm`
+    fn moh_owner(library: &AudioLibrary, realm: &str, id: &str) -> Option<Option<String>> {
+        [Some(realm), None].into_iter().find_map(|owner| {
+            library
+                .get(owner, id)
+                .filter(|file| file.category == AudioCategory::Moh)
+                .map(|_| owner.map(str::to_string))
+        })
+    }
+
+    /// Tenant of a call: the caller's realm when it is branded, else the callee's
+    pub fn tenant_of(&self, caller_uri: &str, callee_uri: &str) -> Option<String> {
+        let tenants = self.tenants.read().unwrap();
+        [caller_uri, callee_uri]
+            .into_iter()
+            .filter_map(realm_of)
+            .find(|realm| tenants.contains_key(*realm))
+            .map(str::to_string)
+    }
+
+    /// MOH source played to calls of `realm`
+    pub fn moh_source(&self, realm: Option<&str>) -> String {
+        let tenant_file = realm.and_then(|realm| {
+            let id = self.get(realm)?.moh_file?;
+            let library = self.library.as_ref()?;
+            let owner = Self::moh_owner(library, realm, &id)?;
+            let file = library.get(owner.as_deref(), &id)?;
+            Some(file.path.to_string_lossy().to_string())
+        });
+        match (tenant_file, realm) {
+            (Some(path), _) => path,
+            (None, Some(realm)) if self.get(realm).and_then(|b| b.moh_file).is_some() => {
+                warn!("MOH file of tenant {} is missing, using the default", realm);
+                self.default_moh_source.clone()
+            }
+            _ => self.default_moh_source.clone(),
+        }
+    }
+
+    /// `request` played in the language and prompt set of `realm`
+    pub fn brand_announcement(
+        &self,
+        realm: Option<&str>,
+        request: AnnouncementRequest,
+    ) -> AnnouncementRequest {
+        let branding = match realm.and_then(|realm| self.get(realm)) {
+            Some(branding) => branding,
+            None => return request,
+        };
+        let request = match branding.language() {
+            Some(language) => request.with_language(language),
+            None => request,
+        };
+        match branding.prompt_set {
+            Some(set) => request.with_prompt_set(&set),
+            None => request,
+        }
+    }
+}
+
+/// Realm (host part) of a SIP URI or name-addr
+pub fn realm_of(uri: &str) -> Option<&str> {
+    uri.split_once('@')
+        .and_then(|(_, host)| host.split([':', ';', '>']).next())
+        .filter(|realm| !realm.is_empty())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::domain::audio::{AudioLibraryConfig, WavFile, WavFormat};
+    use crate::domain::call_announcer::AnnouncementType;
+
+    fn wav(seconds: f64) -> Vec<u8> {
+        let samples = (8000.0 * seconds) as usize;
+        let data: Vec<u8> = (0..samples)
+            .flat_map(|i| (((i % 40) as i16 - 20) * 400).to_le_bytes())
+            .collect();
+        WavFile {
+            format: WavFormat {
+                channels: 1,
+                sample_rate: 8000,
+                bits_per_sample: 16,
+                audio_format: 1,
+            },
+            data: Arc::new(data),
+        }
+        .to_wav_bytes()
+    }
+
+    fn library() -> Arc<AudioLibrary> {
+        let root = std::env::temp_dir().join(format!("yakyak-branding-{}", uuid::Uuid::new_v4()));
+        Arc::new(AudioLibrary::new(AudioLibraryConfig {
+            root,
+            ..Default::default()
+        }))
+    }
+
+    #[test]
+    fn test_patch_keeps_missing_and_clears_null_fields() {
+        let branding = TenantBranding {
+            prompt_language: Some("es".to_string()),
+            display_name: Some("Acme {user}".to_string()),
+            ..Default::default()
+        };
+        let patch: BrandingPatch =
+            serde_json::from_str(r#"{"display_name": null, "prompt_set": "acme"}"#).unwrap();
+
+        let patched = patch.apply(branding);
+        assert_eq!(patched.prompt_language.as_deref(), Some("es"));
+        assert_eq!(patched.display_name, None);
+        assert_eq!(patched.prompt_set.as_deref(), Some("acme"));
+    }
+
+    #[test]
+    fn test_update_requires_existing_moh_file() {
+        let library = library();
+        let registry = BrandingRegistry::new("moh/default.wav".to_string())
+            .with_audio_library(library.clone());
+        let prompt = library
+            .upload(
+                Some("acme.example.com"),
+                "greeting",
+                AudioCategory::Prompt,
+                &wav(0.1),
+            )
+            .unwrap();
+        let moh = library
+            .upload(
+                Some("acme.example.com"),
+                "hold",
+                AudioCategory::Moh,
+                &wav(0.2),
+            )
+            .unwrap();
+        let set_moh = |id: &str| BrandingPatch {
+            moh_file: Some(Some(id.to_string())),
+            ..Default::default()
+        };
+
+        assert!(registry
+            .update("acme.example.com", set_moh("missing"))
+            .is_err());
+        // Prompts are not hold music, and other tenants' files are not visible
+        assert!(registry
+            .update("acme.example.com", set_moh(&prompt.id))
+            .is_err());
+        assert!(registry
+            .update("globex.example.com", set_moh(&moh.id))
+            .is_err());
+
+        registry
+            .update("acme.example.com", set_moh(&moh.id))
+            .unwrap();
+        assert_eq!(
+            registry.moh_source(Some("acme.example.com")),
+            moh.path.to_string_lossy()
+        );
+        assert_eq!(
+            library.references(Some("acme.example.com"), &moh.id),
+            vec![AudioReference::new("branding", "acme.example.com")]
+        );
+        assert_eq!(
+            registry.moh_source(Some("globex.example.com")),
+            "moh/default.wav"
+        );
+        assert_eq!(registry.moh_source(None), "moh/default.wav");
+    }
+
+    #[test]
+    fn test_validation() {
+        let header = |name: &str, value: &str| TenantBranding {
+            header: Some(BrandingHeader {
+                name: name.to_string(),
+                value: value.to_string(),
+            }),
+            ..Default::default()
+        };
+        assert!(header("X-Company", "Acme Corp").validate().is_ok());
+        assert!(header("X Company", "Acme").validate().is_err());
+        assert!(header("X-Company", "Acme\r\nVia: evil").validate().is_err());
+
+        let language = TenantBranding {
+            prompt_language: Some("tlh".to_string()),
+            ..Default::default()
+        };
+        assert!(language.validate().is_err());
+    }
+
+    #[test]
+    fn test_tenant_and_announcement_branding() {
+        let registry = BrandingRegistry::new("moh/default.wav".to_string());
+        registry.set_tenant_branding(
+            "acme.example.com",
+            TenantBranding {
+                prompt_language: Some("es".to_string()),
+                prompt_set: Some("acme".to_string()),
+                display_name: Some("Acme - {display}".to_string()),
+                ..Default::default()
+            },
+        );
+
+        assert_eq!(
+            registry.tenant_of("sip:1001@pbx.local", "sip:alice@acme.example.com"),
+            Some("acme.example.com".to_string())
+        );
+        assert_eq!(
+            registry.tenant_of("sip:1001@pbx.local", "sip:2000@pbx.local"),
+            None
+        );
+
+        let request = registry.brand_announcement(
+            Some("acme.example.com"),
+            AnnouncementRequest::new("call-1".to_string(), AnnouncementType::Custom),
+        );
+        assert_eq!(request.language, Language::Es);
+        assert_eq!(request.prompt_set.as_deref(), Some("acme"));
+
+        let branding = registry.get("acme.example.com").unwrap();
+        assert_eq!(
+            branding
+                .display_name_for("alice", Some("Alice Smith"))
+                .as_deref(),
+            Some("Acme - Alice Smith")
+        );
+        assert_eq!(
+            branding.display_name_for("alice", None).as_deref(),
+            Some("Acme - alice")
+        );
+    }
+}
@@ -0,0 +1,121 @@
+//! Phone directory search
+
+use super::entity::User;
+use serde::{Deserialize, Serialize};
+
+/// Most entries returned by one directory search
+pub const MAX_DIRECTORY_LIMIT: i64 = 200;
+
+/// Search of enabled users by name, extension or department
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DirectoryQuery {
+    /// Text matched case-insensitively anywhere in the display name,
+    /// username (extension) or department; empty matches everyone
+    pub text: String,
+    /// Only users of this realm (tenant)
+    pub realm: Option<String>,
+    pub limit: i64,
+    pub offset: i64,
+}
+
+impl DirectoryQuery {
+    pub fn new(text: impl Into<String>, realm: Option<String>, limit: i64, offset: i64) -> Self {
+        Self {
+            text: text.into().trim().to_string(),
+            realm,
+            limit: limit.clamp(1, MAX_DIRECTORY_LIMIT),
+            offset: offset.max(0),
+        }
+    }
+
+    /// Whether `user` is a result of this query (ignoring paging)
+    pub fn matches(&self, user: &User) -> bool {
+        if !user.enabled
+            || self
+                .realm
+                .as_ref()
+                .is_some_and(|realm| *realm != user.realm)
+        {
+            return false;
+        }
+        let text = self.text.to_lowercase();
+        [
+            Some(&user.username),
+            user.display_name.as_ref(),
+            user.department.as_ref(),
+        ]
+        .into_iter()
+        .flatten()
+        .any(|field| field.to_lowercase().contains(&text))
+    }
+
+    /// `ILIKE` pattern for the text, with `%`, `_` and `\` taken literally
+    pub fn like_pattern(&self) -> String {
+        let mut pattern = String::with_capacity(self.text.len() + 2);
+        pattern.push('%');
+        for c in self.text.chars() {
+            if matches!(c, '%' | '_' | '\\') {
+                pattern.push('\\');
+            }
+            pattern.push(c);
+        }
+        pattern.push('%');
+        pattern
+    }
+
+    /// Name shown for `user`: the display name, else the username
+    pub fn entry_name(user: &User) -> &str {
+        user.display_name
+            .as_deref()
+            .filter(|name| !name.trim().is_empty())
+            .unwrap_or(&user.username)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::Utc;
+
+    fn user(username: &str, display_name: &str, department: Option<&str>) -> User {
+        User {
+            id: 1,
+            username: username.to_string(),
+            password_hash: String::new(),
+            sip_ha1: None,
+            realm: "example.com".to_string(),
+            display_name: Some(display_name.to_string()),
+            email: None,
+            department: department.map(str::to_string),
+            locale: None,
+            timezone: None,
+            enabled: true,
+            role_id: None,
+            created_at: Utc::now(),
+            updated_at: Utc::now(),
+        }
+    }
+
+    #[test]
+    fn test_matches() {
+        let alice = user("1001", "Alice Smith", Some("Sales & Marketing"));
+        assert!(DirectoryQuery::new("smith", None, 50, 0).matches(&alice));
+        assert!(DirectoryQuery::new("100", None, 50, 0).matches(&alice));
+        assert!(DirectoryQuery::new("sales &", None, 50, 0).matches(&alice));
+        assert!(DirectoryQuery::new("", None, 50, 0).matches(&alice));
+        assert!(!DirectoryQuery::new("bob", None, 50, 0).matches(&alice));
+        assert!(!DirectoryQuery::new("", Some("other.com".to_string()), 50, 0).matches(&alice));
+
+        let mut disabled = alice.clone();
+        disabled.enabled = false;
+        assert!(!DirectoryQuery::new("", None, 50, 0).matches(&disabled));
+    }
+
+    #[test]
+    fn test_like_pattern_and_limits() {
+        let query = DirectoryQuery::new(" 50%_off ", None, 10_000, -5);
+        assert_eq!(query.like_pattern(), "%50\\%\\_off%");
+        assert_eq!(query.limit, MAX_DIRECTORY_LIMIT);
+        assert_eq!(query.offset, 0);
+    }
+}
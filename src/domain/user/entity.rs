@@ -15,6 +15,16 @@ pub struct User {
     pub realm: String,
     pub display_name: Option<String>,
     pub email: Option<String>,
+    /// Department shown in the phone directory
+    #[cfg_attr(feature = "postgres", sqlx(default))]
+    pub department: Option<String>,
+    /// Preferred language (BCP 47, e.g. "de-DE"), used as the voicemail
+    /// transcription language hint
+    #[cfg_attr(feature = "postgres", sqlx(default))]
+    pub locale: Option<String>,
+    /// Time zone (IANA, e.g. "Europe/Berlin") of user-scoped API responses
+    #[cfg_attr(feature = "postgres", sqlx(default))]
+    pub timezone: Option<String>,
     pub enabled: bool,
     pub role_id: Option<Uuid>,
     pub created_at: DateTime<Utc>,
@@ -29,6 +39,12 @@ pub struct CreateUser {
     pub realm: String,
     pub display_name: Option<String>,
     pub email: Option<String>,
+    #[serde(default)]
+    pub department: Option<String>,
+    #[serde(default)]
+    pub locale: Option<String>,
+    #[serde(default)]
+    pub timezone: Option<String>,
     pub role_id: Option<Uuid>,
 }
 
@@ -37,6 +53,12 @@ pub struct CreateUser {
 pub struct UpdateUser {
     pub display_name: Option<String>,
     pub email: Option<String>,
+    #[serde(default)]
+    pub department: Option<String>,
+    #[serde(default)]
+    pub locale: Option<String>,
+    #[serde(default)]
+    pub timezone: Option<String>,
     pub enabled: Option<bool>,
     pub role_id: Option<Uuid>,
 }
@@ -1,10 +1,12 @@
 //! User domain
 
+pub mod directory;
 pub mod entity;
 pub mod repository;
 pub mod role;
 pub mod role_repository;
 
+pub use directory::{DirectoryQuery, MAX_DIRECTORY_LIMIT};
 pub use entity::{ChangePassword, CreateUser, UpdateUser, User};
 pub use repository::UserRepository;
 pub use role::{Permission, Role};
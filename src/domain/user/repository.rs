@@ -1,10 +1,13 @@
 //! User repository interface
 
+use super::directory::DirectoryQuery;
 use super::entity::{ChangePassword, CreateUser, UpdateUser, User};
 use crate::domain::shared::error::Result;
+use crate::domain::shared::SortOrder;
 use async_trait::async_trait;
 
 /// User repository trait
+#[cfg_attr(test, mockall::automock)]
 #[async_trait]
 pub trait UserRepository: Send + Sync {
     /// Create a new user
@@ -20,11 +23,18 @@ pub trait UserRepository: Send + Sync {
     async fn find_by_username_and_realm(&self, username: &str, realm: &str)
         -> Result<Option<User>>;
 
-    /// List all users
-    async fn list(&self, limit: i64, offset: i64) -> Result<Vec<User>>;
+    /// List all users (sortable by id, username, display_name, realm,
+    /// created_at)
+    async fn list(&self, sort: &SortOrder, limit: i64, offset: i64) -> Result<Vec<User>>;
 
     /// List users by realm
-    async fn list_by_realm(&self, realm: &str, limit: i64, offset: i64) -> Result<Vec<User>>;
+    async fn list_by_realm(
+        &self,
+        realm: &str,
+        sort: &SortOrder,
+        limit: i64,
+        offset: i64,
+    ) -> Result<Vec<User>>;
 
     /// Update user
     async fn update(&self, id: i32, data: UpdateUser) -> Result<User>;
@@ -44,6 +54,9 @@ pub trait UserRepository: Send + Sync {
     /// Count users by realm
     async fn count_by_realm(&self, realm: &str) -> Result<i64>;
 
+    /// Enabled users matching a directory query, ordered by name
+    async fn search(&self, query: &DirectoryQuery) -> Result<Vec<User>>;
+
     /// Verify user credentials
     async fn verify_credentials(&self, username: &str, password: &str) -> Result<Option<User>>;
 }
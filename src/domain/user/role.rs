@@ -3,7 +3,7 @@ use std::collections::HashSet;
 use uuid::Uuid;
 
 /// User role with associated permissions
-#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq, Hash)]
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
 pub struct Role {
     pub id: Uuid,
     pub name: String,
@@ -46,6 +46,9 @@ pub enum Permission {
     // Voicemail
     VoicemailAccess,
     VoicemailManage,
+
+    // Privacy
+    PrivacyErasure,
 }
 
 impl Permission {
@@ -72,6 +75,7 @@ impl Permission {
             Permission::ConferenceModerate,
             Permission::VoicemailAccess,
             Permission::VoicemailManage,
+            Permission::PrivacyErasure,
         ])
     }
 
@@ -98,6 +102,7 @@ impl Permission {
             Permission::ConferenceModerate => "conference:moderate",
             Permission::VoicemailAccess => "voicemail:access",
             Permission::VoicemailManage => "voicemail:manage",
+            Permission::PrivacyErasure => "privacy:erasure",
         }
     }
 
@@ -124,6 +129,7 @@ impl Permission {
             "conference:moderate" => Some(Permission::ConferenceModerate),
             "voicemail:access" => Some(Permission::VoicemailAccess),
             "voicemail:manage" => Some(Permission::VoicemailManage),
+            "privacy:erasure" => Some(Permission::PrivacyErasure),
             _ => None,
         }
     }
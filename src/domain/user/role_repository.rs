@@ -5,6 +5,7 @@ use uuid::Uuid;
 use super::role::{Permission, Role};
 
 /// Repository for role management
+#[cfg_attr(test, mockall::automock)]
 #[async_trait]
 pub trait RoleRepository: Send + Sync {
     /// Create a new role
@@ -26,6 +26,16 @@ pub struct VoicemailMessage {
     pub created_at: DateTime<Utc>,
     pub read_at: Option<DateTime<Utc>>,
     pub saved_at: Option<DateTime<Utc>>,
+    /// Speech-to-text of the recording, once transcribed
+    #[serde(default)]
+    pub transcript: Option<String>,
+    /// Transcription confidence (0.0 - 1.0)
+    #[serde(default)]
+    pub transcript_confidence: Option<f32>,
+    /// Name of the distribution list the message was sent to, when it was
+    /// delivered to every member of one
+    #[serde(default)]
+    pub via_list: Option<String>,
 }
 
 impl VoicemailMessage {
@@ -49,6 +59,39 @@ impl VoicemailMessage {
             created_at: Utc::now(),
             read_at: None,
             saved_at: None,
+            transcript: None,
+            transcript_confidence: None,
+            via_list: None,
+        }
+    }
+
+    /// Mark the message as delivered through a distribution list
+    pub fn with_via_list(mut self, list_name: String) -> Self {
+        self.via_list = Some(list_name);
+        self
+    }
+
+    /// Copy of the message for another mailbox, sharing the recording
+    pub fn forward_to(&self, mailbox_id: String) -> Self {
+        Self {
+            id: Uuid::new_v4(),
+            mailbox_id,
+            status: VoicemailStatus::New,
+            created_at: Utc::now(),
+            read_at: None,
+            saved_at: None,
+            via_list: None,
+            ..self.clone()
+        }
+    }
+
+    /// Caller as shown to the recipient: `Bob (via Sales Team)` for list
+    /// messages
+    pub fn display_caller(&self) -> String {
+        let caller = self.caller_name.as_deref().unwrap_or(&self.caller);
+        match &self.via_list {
+            Some(list) => format!("{} (via {})", caller, list),
+            None => caller.to_string(),
         }
     }
 
@@ -75,6 +118,42 @@ impl VoicemailMessage {
     pub fn is_new(&self) -> bool {
         self.status == VoicemailStatus::New
     }
+
+    /// Subject and body of the new-message email
+    ///
+    /// The transcript is included when there is one; otherwise the email
+    /// only announces the message.
+    pub fn notification_email(&self) -> (String, String) {
+        let from = match &self.caller_name {
+            Some(name) => format!("{} <{}>", name, self.caller),
+            None => self.caller.clone(),
+        };
+        let from = match &self.via_list {
+            Some(list) => format!("{} via {}", from, list),
+            None => from,
+        };
+        let subject = format!("New voicemail from {}", from);
+
+        let mut body = format!(
+            "You have a new voicemail in mailbox {}.\n\nFrom: {}\nReceived: {}\nDuration: {}:{:02}\n",
+            self.mailbox_id,
+            from,
+            self.created_at.format("%Y-%m-%d %H:%M UTC"),
+            self.duration_seconds / 60,
+            self.duration_seconds % 60
+        );
+        match &self.transcript {
+            Some(text) => {
+                body.push_str("\nTranscript");
+                if let Some(confidence) = self.transcript_confidence {
+                    body.push_str(&format!(" ({:.0}% confidence)", confidence * 100.0));
+                }
+                body.push_str(&format!(":\n{}\n", text));
+            }
+            None => body.push_str("\nNo transcript is available for this message.\n"),
+        }
+        (subject, body)
+    }
 }
 
 /// Voicemail mailbox configuration
@@ -84,6 +163,9 @@ pub struct VoicemailMailbox {
     pub user_id: i32,
     pub pin: Option<String>,
     pub greeting_file: Option<String>,
+    /// Recorded name, read back by dial-by-name directories
+    #[serde(default)]
+    pub name_greeting_file: Option<String>,
     pub max_message_duration: u32, // seconds
     pub max_messages: u32,
     pub email_notification: bool,
@@ -99,6 +181,7 @@ impl VoicemailMailbox {
             user_id,
             pin: None,
             greeting_file: None,
+            name_greeting_file: None,
             max_message_duration: 180, // 3 minutes default
             max_messages: 100,
             email_notification: false,
@@ -132,12 +215,21 @@ pub trait VoicemailRepository: Send + Sync {
     /// Update message status
     async fn update_message_status(&self, id: Uuid, status: VoicemailStatus) -> Result<(), String>;
 
+    /// Store the transcript of a message
+    async fn set_transcript(&self, id: Uuid, transcript: &str, confidence: Option<f32>) -> Result<(), String>;
+
     /// Delete message (permanent)
     async fn delete_message(&self, id: Uuid) -> Result<(), String>;
 
     /// Count messages for a mailbox
     async fn count_messages(&self, mailbox_id: &str, status: Option<VoicemailStatus>) -> Result<u32, String>;
 
+    /// Count messages, in any mailbox, whose audio is stored at `audio_file_path`
+    ///
+    /// A message sent to a distribution list shares one recording between
+    /// the members' copies; the file is deleted with the last of them.
+    async fn count_audio_references(&self, audio_file_path: &str) -> Result<u32, String>;
+
     /// Get mailbox configuration
     async fn get_mailbox(&self, mailbox_id: &str) -> Result<Option<VoicemailMailbox>, String>;
 
@@ -248,6 +340,28 @@ mod tests {
         assert!(message.saved_at.is_some());
     }
 
+    #[test]
+    fn test_notification_email_includes_transcript() {
+        let mut message = VoicemailMessage::new(
+            "alice".to_string(),
+            "sip:bob@example.com".to_string(),
+            Some("Bob".to_string()),
+            75,
+            "/var/voicemail/alice/msg001.wav".to_string(),
+            "wav".to_string(),
+        );
+
+        let (subject, body) = message.notification_email();
+        assert_eq!(subject, "New voicemail from Bob <sip:bob@example.com>");
+        assert!(body.contains("Duration: 1:15"));
+        assert!(body.contains("No transcript"));
+
+        message.transcript = Some("Call me back about the invoice".to_string());
+        message.transcript_confidence = Some(0.87);
+        let (_, body) = message.notification_email();
+        assert!(body.contains("Transcript (87% confidence):\nCall me back about the invoice"));
+    }
+
     #[test]
     fn test_voicemail_mailbox() {
         let mailbox = VoicemailMailbox::new("alice".to_string(), 1);
@@ -1,6 +1,7 @@
 /// Voicemail IVR (Interactive Voice Response) for dial-in access
+use crate::domain::audio::{digit_prompts, time_prompts, Language};
+use crate::domain::queue_callback::caller_number;
 use crate::domain::voicemail::{VoicemailMessage, VoicemailMailbox, VoicemailStatus};
-use crate::domain::voicemail_service::{VoicemailPlayer, MwiState};
 use std::collections::HashMap;
 use uuid::Uuid;
 
@@ -19,6 +20,14 @@ pub enum VoicemailIvrState {
     MessageOptions,
     /// Recording greeting
     RecordingGreeting,
+    /// Entering the number of a distribution list
+    EnteringListNumber,
+    /// Recording a message for a distribution list
+    RecordingListMessage,
+    /// Entering the mailbox to forward the current message to
+    EnteringForwardMailbox,
+    /// Recording the comment put before a forwarded message
+    RecordingForwardComment,
     /// Finished/hung up
     Finished,
 }
@@ -44,6 +53,8 @@ pub enum VoicemailMenuOption {
     Exit,
     /// Record greeting (9)
     RecordGreeting,
+    /// Send a message to a distribution list (7)
+    SendToList,
 }
 
 impl VoicemailMenuOption {
@@ -59,6 +70,7 @@ impl VoicemailMenuOption {
             '*' => Some(Self::MainMenu),
             '#' => Some(Self::Exit),
             '9' => Some(Self::RecordGreeting),
+            '7' => Some(Self::SendToList),
             _ => None,
         }
     }
@@ -75,10 +87,74 @@ impl VoicemailMenuOption {
             Self::MainMenu => '*',
             Self::Exit => '#',
             Self::RecordGreeting => '9',
+            Self::SendToList => '7',
         }
     }
 }
 
+/// What to do with a message once it was played
+///
+/// Offered in the message options menu, whose digits differ from the main
+/// menu's.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum VoicemailMessageAction {
+    /// Play the next message (1)
+    Next,
+    /// Replay the message (2)
+    Replay,
+    /// Delete the message (3)
+    Delete,
+    /// Call the sender back (5)
+    CallBack,
+    /// Forward to another mailbox, with an optional comment (6)
+    Forward,
+    /// Save to the old messages (9)
+    SaveOld,
+    /// Exit voicemail (#)
+    Exit,
+}
+
+impl VoicemailMessageAction {
+    /// Get action from DTMF digit
+    pub fn from_digit(digit: char) -> Option<Self> {
+        match digit {
+            '1' => Some(Self::Next),
+            '2' => Some(Self::Replay),
+            '3' => Some(Self::Delete),
+            '5' => Some(Self::CallBack),
+            '6' => Some(Self::Forward),
+            '9' => Some(Self::SaveOld),
+            '#' => Some(Self::Exit),
+            _ => None,
+        }
+    }
+
+    /// Get digit for action
+    pub fn to_digit(self) -> char {
+        match self {
+            Self::Next => '1',
+            Self::Replay => '2',
+            Self::Delete => '3',
+            Self::CallBack => '5',
+            Self::Forward => '6',
+            Self::SaveOld => '9',
+            Self::Exit => '#',
+        }
+    }
+}
+
+/// Prompts played before a message: when it was received (UTC) and the
+/// caller's number, read digit by digit when the caller ID has one
+pub fn envelope_prompts(message: &VoicemailMessage, language: Language) -> Vec<String> {
+    let mut prompts = vec![VoicemailPrompt::MessageReceived.audio_id().to_string()];
+    prompts.extend(time_prompts(message.created_at.time(), language));
+    if let Some(number) = caller_number(&message.caller) {
+        prompts.push(VoicemailPrompt::MessageFrom.audio_id().to_string());
+        prompts.extend(digit_prompts(&number));
+    }
+    prompts
+}
+
 /// Voicemail IVR session
 pub struct VoicemailIvrSession {
     /// Session ID
@@ -95,6 +171,10 @@ pub struct VoicemailIvrSession {
     current_message_index: usize,
     /// List of messages
     messages: Vec<VoicemailMessage>,
+    /// Distribution list number being entered
+    list_number_buffer: String,
+    /// Mailbox being entered to forward the current message to
+    forward_mailbox_buffer: String,
     /// Session variables
     variables: HashMap<String, String>,
 }
@@ -110,6 +190,8 @@ impl VoicemailIvrSession {
             pin_attempts: 3,
             current_message_index: 0,
             messages: Vec::new(),
+            list_number_buffer: String::new(),
+            forward_mailbox_buffer: String::new(),
             variables: HashMap::new(),
         }
     }
@@ -256,6 +338,69 @@ impl VoicemailIvrSession {
         }
     }
 
+    /// Start addressing a message to a distribution list
+    pub fn start_list_message(&mut self) {
+        self.list_number_buffer.clear();
+        self.state = VoicemailIvrState::EnteringListNumber;
+    }
+
+    /// Add a digit of the list number
+    pub fn add_list_digit(&mut self, digit: char) {
+        if digit.is_ascii_digit() {
+            self.list_number_buffer.push(digit);
+        }
+    }
+
+    /// Get entered list number
+    pub fn list_number(&self) -> &str {
+        &self.list_number_buffer
+    }
+
+    /// The list number was accepted; record the message
+    pub fn record_list_message(&mut self) {
+        self.state = VoicemailIvrState::RecordingListMessage;
+    }
+
+    /// Back to the main menu once the list message was sent or refused
+    pub fn finish_list_message(&mut self) {
+        self.list_number_buffer.clear();
+        self.state = VoicemailIvrState::MainMenu;
+    }
+
+    /// Messages were loaded and the current one played; wait for an action
+    pub fn offer_message_options(&mut self) {
+        self.state = VoicemailIvrState::MessageOptions;
+    }
+
+    /// Start forwarding the current message
+    pub fn start_forward(&mut self) {
+        self.forward_mailbox_buffer.clear();
+        self.state = VoicemailIvrState::EnteringForwardMailbox;
+    }
+
+    /// Add a digit of the mailbox to forward to
+    pub fn add_forward_digit(&mut self, digit: char) {
+        if digit.is_ascii_digit() {
+            self.forward_mailbox_buffer.push(digit);
+        }
+    }
+
+    /// Get entered mailbox to forward to
+    pub fn forward_mailbox(&self) -> &str {
+        &self.forward_mailbox_buffer
+    }
+
+    /// The mailbox was accepted; record the comment
+    pub fn record_forward_comment(&mut self) {
+        self.state = VoicemailIvrState::RecordingForwardComment;
+    }
+
+    /// Back to the message options once the message was forwarded or not
+    pub fn finish_forward(&mut self) {
+        self.forward_mailbox_buffer.clear();
+        self.state = VoicemailIvrState::MessageOptions;
+    }
+
     /// Set session variable
     pub fn set_variable(&mut self, key: String, value: String) {
         self.variables.insert(key, value);
@@ -317,6 +462,35 @@ pub enum VoicemailPrompt {
     RecordGreeting,
     /// Greeting recorded
     GreetingRecorded,
+    /// Mailbox is full, no message can be left
+    MailboxFull,
+    /// Enter the number of the distribution list
+    EnterListNumber,
+    /// Record your message for the list
+    RecordListMessage,
+    /// Message sent to the list
+    ListMessageSent,
+    /// No such list, or not allowed to send to it
+    ListUnavailable,
+    /// Received at <time>
+    MessageReceived,
+    /// From <number>
+    MessageFrom,
+    /// Message options: 1 next, 2 replay, 3 delete, 5 call back, 6 forward,
+    /// 9 save
+    MessageActions,
+    /// Calling the sender back
+    CallingBack,
+    /// The sender cannot be called back
+    CallbackUnavailable,
+    /// Enter the mailbox to forward to
+    EnterForwardMailbox,
+    /// Record a comment after the tone, # when done
+    RecordForwardComment,
+    /// Message forwarded
+    MessageForwarded,
+    /// No such mailbox to forward to
+    ForwardUnavailable,
     /// Goodbye
     Goodbye,
 }
@@ -338,6 +512,20 @@ impl VoicemailPrompt {
             Self::NoMoreMessages => "vm_no_more",
             Self::RecordGreeting => "vm_record_greeting",
             Self::GreetingRecorded => "vm_greeting_saved",
+            Self::MailboxFull => "vm_mailbox_full",
+            Self::EnterListNumber => "vm_enter_list_number",
+            Self::RecordListMessage => "vm_record_list_message",
+            Self::ListMessageSent => "vm_list_message_sent",
+            Self::ListUnavailable => "vm_list_unavailable",
+            Self::MessageReceived => "vm_received",
+            Self::MessageFrom => "vm_from",
+            Self::MessageActions => "vm_message_actions",
+            Self::CallingBack => "vm_calling_back",
+            Self::CallbackUnavailable => "vm_callback_unavailable",
+            Self::EnterForwardMailbox => "vm_enter_forward_mailbox",
+            Self::RecordForwardComment => "vm_record_comment",
+            Self::MessageForwarded => "vm_forwarded",
+            Self::ForwardUnavailable => "vm_forward_unavailable",
             Self::Goodbye => "vm_goodbye",
         }
     }
@@ -546,6 +734,85 @@ mod tests {
         assert_eq!(session.total_message_count(), 2);
     }
 
+    #[test]
+    fn test_list_number_entry() {
+        let mut session = VoicemailIvrSession::new();
+        session.state = VoicemailIvrState::MainMenu;
+        assert_eq!(
+            VoicemailMenuOption::from_digit('7'),
+            Some(VoicemailMenuOption::SendToList)
+        );
+
+        session.start_list_message();
+        assert_eq!(session.state, VoicemailIvrState::EnteringListNumber);
+        for digit in ['8', '0', '*', '1'] {
+            session.add_list_digit(digit);
+        }
+        assert_eq!(session.list_number(), "801");
+
+        session.record_list_message();
+        assert_eq!(session.state, VoicemailIvrState::RecordingListMessage);
+
+        session.finish_list_message();
+        assert_eq!(session.state, VoicemailIvrState::MainMenu);
+        assert_eq!(session.list_number(), "");
+    }
+
+    #[test]
+    fn test_message_actions_and_forward_entry() {
+        assert_eq!(
+            VoicemailMessageAction::from_digit('5'),
+            Some(VoicemailMessageAction::CallBack)
+        );
+        assert_eq!(
+            VoicemailMessageAction::from_digit('6'),
+            Some(VoicemailMessageAction::Forward)
+        );
+        assert_eq!(VoicemailMessageAction::SaveOld.to_digit(), '9');
+        assert_eq!(VoicemailMessageAction::from_digit('4'), None);
+
+        let mut session = VoicemailIvrSession::new();
+        session.offer_message_options();
+        session.start_forward();
+        assert_eq!(session.state, VoicemailIvrState::EnteringForwardMailbox);
+        for digit in ['2', '0', '*', '1'] {
+            session.add_forward_digit(digit);
+        }
+        assert_eq!(session.forward_mailbox(), "201");
+        session.record_forward_comment();
+        assert_eq!(session.state, VoicemailIvrState::RecordingForwardComment);
+        session.finish_forward();
+        assert_eq!(session.state, VoicemailIvrState::MessageOptions);
+        assert_eq!(session.forward_mailbox(), "");
+    }
+
+    #[test]
+    fn test_envelope_prompts() {
+        let mut message = VoicemailMessage::new(
+            "alice".to_string(),
+            "sip:+4420@example.com".to_string(),
+            None,
+            30,
+            "msg1.wav".to_string(),
+            "wav".to_string(),
+        );
+        message.created_at = "2025-11-08T14:05:00Z".parse().unwrap();
+        assert_eq!(
+            envelope_prompts(&message, Language::En),
+            vec![
+                "vm_received", "number_2", "time_oh", "number_5", "time_pm", "vm_from",
+                "digit_plus", "digit_4", "digit_4", "digit_2", "digit_0",
+            ]
+        );
+
+        // Anonymous callers have no number to read
+        message.caller = "sip:anonymous@anonymous.invalid".to_string();
+        assert_eq!(
+            envelope_prompts(&message, Language::De),
+            vec!["vm_received", "number_14", "time_hours", "number_5"]
+        );
+    }
+
     #[test]
     fn test_prompt_audio_ids() {
         assert_eq!(VoicemailPrompt::Welcome.audio_id(), "vm_welcome");
@@ -0,0 +1,384 @@
+//! Voicemail distribution lists
+//!
+//! A manager records one message and addresses it to a list number from the
+//! voicemail menu; every member mailbox gets its own copy. The copies share
+//! the recording (the file is deleted with the last copy) and carry the
+//! list name, so the recipients see "Bob (via Sales Team)". Members are read
+//! when the message is sent: later membership changes do not touch
+//! delivered messages.
+
+use crate::domain::voicemail::{VoicemailMessage, VoicemailRepository};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+use tracing::{info, warn};
+use uuid::Uuid;
+
+/// Most members any list may have
+pub const MAX_LIST_MEMBERS: u32 = 500;
+
+/// Member limit of a list unless set otherwise
+pub const DEFAULT_MAX_MEMBERS: u32 = 50;
+
+/// Mailboxes allowed to send to a list, besides its owner
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "scope", rename_all = "snake_case")]
+pub enum ListSendPermission {
+    /// Only the owner
+    #[default]
+    Owner,
+    /// The owner and the members
+    Members,
+    /// Any mailbox
+    Everyone,
+    /// The owner and these mailboxes
+    Mailboxes { mailboxes: Vec<String> },
+}
+
+/// Group of mailboxes reached through one list number
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct VoicemailDistributionList {
+    pub id: Uuid,
+    /// Shown to the recipients, e.g. "Sales Team"
+    pub name: String,
+    /// Number entered on the keypad to address the list
+    pub number: String,
+    /// Mailbox of the list owner
+    pub owner: String,
+    /// Member mailboxes
+    pub members: Vec<String>,
+    /// Most members the list may have (at most [`MAX_LIST_MEMBERS`])
+    pub max_members: u32,
+    pub senders: ListSendPermission,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+}
+
+impl VoicemailDistributionList {
+    pub fn new(name: String, number: String, owner: String) -> Self {
+        let now = Utc::now();
+        Self {
+            id: Uuid::new_v4(),
+            name,
+            number,
+            owner,
+            members: Vec::new(),
+            max_members: DEFAULT_MAX_MEMBERS,
+            senders: ListSendPermission::Owner,
+            created_at: now,
+            updated_at: now,
+        }
+    }
+
+    pub fn with_member(mut self, mailbox_id: &str) -> Self {
+        self.members.push(mailbox_id.to_string());
+        self
+    }
+
+    pub fn with_senders(mut self, senders: ListSendPermission) -> Self {
+        self.senders = senders;
+        self
+    }
+
+    pub fn validate(&self) -> Result<(), String> {
+        if self.name.trim().is_empty() {
+            return Err("Name is required".to_string());
+        }
+        if self.number.is_empty() || !self.number.chars().all(|c| c.is_ascii_digit()) {
+            return Err(format!("List number must be digits: {}", self.number));
+        }
+        if self.owner.is_empty() {
+            return Err("Owner mailbox is required".to_string());
+        }
+        if !(1..=MAX_LIST_MEMBERS).contains(&self.max_members) {
+            return Err(format!("Member limit must be between 1 and {}", MAX_LIST_MEMBERS));
+        }
+        if self.members.len() > self.max_members as usize {
+            return Err(format!(
+                "List has {} members, its limit is {}",
+                self.members.len(),
+                self.max_members
+            ));
+        }
+        if self.members.iter().any(String::is_empty) {
+            return Err("Member mailbox is empty".to_string());
+        }
+        if let Some(duplicate) = self
+            .members
+            .iter()
+            .enumerate()
+            .find(|(i, member)| self.members[..*i].contains(member))
+            .map(|(_, member)| member)
+        {
+            return Err(format!("Mailbox {} is listed twice", duplicate));
+        }
+        Ok(())
+    }
+
+    /// Whether `mailbox_id` may send messages to the list
+    pub fn may_send(&self, mailbox_id: &str) -> bool {
+        if mailbox_id == self.owner {
+            return true;
+        }
+        match &self.senders {
+            ListSendPermission::Owner => false,
+            ListSendPermission::Members => self.members.iter().any(|m| m == mailbox_id),
+            ListSendPermission::Everyone => true,
+            ListSendPermission::Mailboxes { mailboxes } => mailboxes.iter().any(|m| m == mailbox_id),
+        }
+    }
+}
+
+/// Distribution list repository trait
+#[async_trait::async_trait]
+pub trait VoicemailListRepository: Send + Sync {
+    async fn create(&self, list: &VoicemailDistributionList) -> Result<(), String>;
+
+    async fn get_by_id(&self, id: Uuid) -> Result<Option<VoicemailDistributionList>, String>;
+
+    async fn find_by_number(&self, number: &str) -> Result<Option<VoicemailDistributionList>, String>;
+
+    async fn list(&self) -> Result<Vec<VoicemailDistributionList>, String>;
+
+    async fn update(&self, list: &VoicemailDistributionList) -> Result<(), String>;
+
+    async fn delete(&self, id: Uuid) -> Result<(), String>;
+}
+
+/// Distribution list errors
+#[derive(Debug, Clone, PartialEq)]
+pub enum VoicemailListError {
+    Invalid(String),
+    NotFound(Uuid),
+    /// No list has the dialed number
+    UnknownNumber(String),
+    /// Another list has the number
+    Duplicate(String),
+    /// The mailbox may not send to the list
+    NotAllowed { mailbox: String, list: String },
+    Repository(String),
+}
+
+impl std::fmt::Display for VoicemailListError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            VoicemailListError::Invalid(e) => write!(f, "{}", e),
+            VoicemailListError::NotFound(id) => write!(f, "Distribution list not found: {}", id),
+            VoicemailListError::UnknownNumber(number) => {
+                write!(f, "No distribution list has number {}", number)
+            }
+            VoicemailListError::Duplicate(number) => {
+                write!(f, "Number {} is already used by a distribution list", number)
+            }
+            VoicemailListError::NotAllowed { mailbox, list } => {
+                write!(f, "Mailbox {} may not send to {}", mailbox, list)
+            }
+            VoicemailListError::Repository(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl std::error::Error for VoicemailListError {}
+
+/// Manages distribution lists and delivers messages to their members
+pub struct VoicemailListService {
+    repository: Arc<dyn VoicemailListRepository>,
+    /// Receives the members' copies (wrapped for MWI in production)
+    voicemail: Arc<dyn VoicemailRepository>,
+}
+
+impl VoicemailListService {
+    pub fn new(
+        repository: Arc<dyn VoicemailListRepository>,
+        voicemail: Arc<dyn VoicemailRepository>,
+    ) -> Self {
+        Self { repository, voicemail }
+    }
+
+    pub async fn list(&self) -> Result<Vec<VoicemailDistributionList>, VoicemailListError> {
+        self.repository
+            .list()
+            .await
+            .map_err(VoicemailListError::Repository)
+    }
+
+    pub async fn get(&self, id: Uuid) -> Result<VoicemailDistributionList, VoicemailListError> {
+        self.repository
+            .get_by_id(id)
+            .await
+            .map_err(VoicemailListError::Repository)?
+            .ok_or(VoicemailListError::NotFound(id))
+    }
+
+    pub async fn create(
+        &self,
+        list: VoicemailDistributionList,
+    ) -> Result<VoicemailDistributionList, VoicemailListError> {
+        self.check(&list).await?;
+        self.repository
+            .create(&list)
+            .await
+            .map_err(VoicemailListError::Repository)?;
+        info!(
+            "Distribution list {} ({}) created with {} members",
+            list.name,
+            list.number,
+            list.members.len()
+        );
+        Ok(list)
+    }
+
+    /// Replace a list's settings, keeping its id and creation time
+    pub async fn update(
+        &self,
+        id: Uuid,
+        list: VoicemailDistributionList,
+    ) -> Result<VoicemailDistributionList, VoicemailListError> {
+        let current = self.get(id).await?;
+        let list = VoicemailDistributionList {
+            id,
+            created_at: current.created_at,
+            updated_at: Utc::now(),
+            ..list
+        };
+        self.check(&list).await?;
+        self.repository
+            .update(&list)
+            .await
+            .map_err(VoicemailListError::Repository)?;
+        info!("Distribution list {} updated", id);
+        Ok(list)
+    }
+
+    pub async fn delete(&self, id: Uuid) -> Result<(), VoicemailListError> {
+        self.get(id).await?;
+        self.repository
+            .delete(id)
+            .await
+            .map_err(VoicemailListError::Repository)?;
+        info!("Distribution list {} deleted", id);
+        Ok(())
+    }
+
+    /// List dialed by `sender` in the voicemail menu, if it may send to it
+    pub async fn resolve(
+        &self,
+        number: &str,
+        sender: &str,
+    ) -> Result<VoicemailDistributionList, VoicemailListError> {
+        let list = self
+            .repository
+            .find_by_number(number)
+            .await
+            .map_err(VoicemailListError::Repository)?
+            .ok_or_else(|| VoicemailListError::UnknownNumber(number.to_string()))?;
+        if !list.may_send(sender) {
+            return Err(VoicemailListError::NotAllowed {
+                mailbox: sender.to_string(),
+                list: list.name.clone(),
+            });
+        }
+        Ok(list)
+    }
+
+    /// Put a copy of `message` into every member mailbox
+    ///
+    /// The copies keep the message's audio file. A member whose copy cannot
+    /// be stored is skipped; the copies delivered are returned.
+    pub async fn deliver(
+        &self,
+        list: &VoicemailDistributionList,
+        message: &VoicemailMessage,
+    ) -> Result<Vec<VoicemailMessage>, VoicemailListError> {
+        let mut delivered = Vec::with_capacity(list.members.len());
+        for member in &list.members {
+            let copy = VoicemailMessage {
+                id: Uuid::new_v4(),
+                mailbox_id: member.clone(),
+                ..message.clone()
+            }
+            .with_via_list(list.name.clone());
+            match self.voicemail.create_message(copy).await {
+                Ok(copy) => delivered.push(copy),
+                Err(e) => warn!("Failed to deliver list message to {}: {}", member, e),
+            }
+        }
+        if delivered.is_empty() && !list.members.is_empty() {
+            return Err(VoicemailListError::Repository(format!(
+                "No member of {} received the message",
+                list.name
+            )));
+        }
+        info!(
+            "Message from {} delivered to {} of {} members of {}",
+            message.caller,
+            delivered.len(),
+            list.members.len(),
+            list.name
+        );
+        Ok(delivered)
+    }
+
+    /// Validate a list and check that its number is free
+    async fn check(&self, list: &VoicemailDistributionList) -> Result<(), VoicemailListError> {
+        list.validate().map_err(VoicemailListError::Invalid)?;
+        let existing = self
+            .repository
+            .find_by_number(&list.number)
+            .await
+            .map_err(VoicemailListError::Repository)?;
+        if existing.is_some_and(|existing| existing.id != list.id) {
+            return Err(VoicemailListError::Duplicate(list.number.clone()));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sales() -> VoicemailDistributionList {
+        VoicemailDistributionList::new("Sales Team".to_string(), "801".to_string(), "manager".to_string())
+            .with_member("alice")
+            .with_member("bob")
+    }
+
+    #[test]
+    fn test_validate() {
+        assert!(sales().validate().is_ok());
+
+        let mut list = sales();
+        list.number = "80a".to_string();
+        assert!(list.validate().is_err());
+
+        let mut list = sales().with_member("alice");
+        assert_eq!(list.validate().unwrap_err(), "Mailbox alice is listed twice");
+
+        list.members.pop();
+        list.max_members = 1;
+        assert!(list.validate().unwrap_err().contains("its limit is 1"));
+
+        list.max_members = MAX_LIST_MEMBERS + 1;
+        assert!(list.validate().is_err());
+    }
+
+    #[test]
+    fn test_send_permission() {
+        let list = sales();
+        assert!(list.may_send("manager"));
+        assert!(!list.may_send("alice"));
+
+        let list = sales().with_senders(ListSendPermission::Members);
+        assert!(list.may_send("alice"));
+        assert!(!list.may_send("carol"));
+
+        let list = sales().with_senders(ListSendPermission::Mailboxes {
+            mailboxes: vec!["carol".to_string()],
+        });
+        assert!(list.may_send("carol"));
+        assert!(!list.may_send("bob"));
+
+        assert!(sales().with_senders(ListSendPermission::Everyone).may_send("anyone"));
+    }
+}
@@ -1,7 +1,10 @@
 /// Voicemail recording and playback services
-use crate::domain::audio::wav::{WavFile, WavFormat};
+use crate::domain::audio::wav::{WavFile, NARROWBAND_RATE};
 use crate::domain::audio::player::{AudioPlayer, PlaybackOptions};
-use crate::domain::voicemail::{VoicemailMessage, VoicemailMailbox};
+use crate::domain::voicemail::{VoicemailMessage, VoicemailMailbox, VoicemailRepository};
+use crate::domain::voicemail_ivr::VoicemailPrompt;
+use crate::domain::voicemail_list::VoicemailDistributionList;
+use crate::infrastructure::storage::{StorageCategory, StorageError, StorageGuard};
 use std::fs::{self, File};
 use std::io::Write;
 use std::path::{Path, PathBuf};
@@ -150,6 +153,16 @@ impl VoicemailRecorder {
         self.buffer.len()
     }
 
+    /// Recorded samples (16-bit PCM mono)
+    pub fn samples(&self) -> &[i16] {
+        &self.buffer
+    }
+
+    /// Get recording sample rate
+    pub fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
     /// Clear the recording buffer
     pub fn clear(&mut self) {
         self.buffer.clear();
@@ -190,11 +203,9 @@ impl VoicemailPlayer {
             self.base_dir.join(&message.audio_file_path)
         };
 
-        let wav_file = WavFile::from_file(&audio_path)
-            .map_err(|e| format!("Failed to load audio file: {:?}", e))?;
-
-        // Convert to G.711 compatible format if needed
-        let compatible = wav_file.to_g711_compatible();
+        // Converted to 8 kHz mono as it is read
+        let compatible = WavFile::from_file_converted(&audio_path, NARROWBAND_RATE)
+            .map_err(|e| format!("Failed to load audio file: {}", e))?;
 
         self.player.load(Arc::new(compatible));
         self.player.play();
@@ -212,10 +223,8 @@ impl VoicemailPlayer {
                 self.base_dir.join(greeting_file)
             };
 
-            let wav_file = WavFile::from_file(&greeting_path)
-                .map_err(|e| format!("Failed to load greeting file: {:?}", e))?;
-
-            let compatible = wav_file.to_g711_compatible();
+            let compatible = WavFile::from_file_converted(&greeting_path, NARROWBAND_RATE)
+                .map_err(|e| format!("Failed to load greeting file: {}", e))?;
             self.player.load(Arc::new(compatible));
             self.player.play();
             Ok(())
@@ -285,10 +294,23 @@ impl VoicemailPlayer {
     }
 }
 
+/// How a caller leaving a message continues
+pub enum Deposit {
+    /// Record the message
+    Record(VoicemailRecorder),
+    /// Play the prompt instead of recording
+    Refused {
+        prompt: VoicemailPrompt,
+        reason: StorageError,
+    },
+}
+
 /// Voicemail service for managing recordings and playback
 pub struct VoicemailService {
     /// Base directory for voicemail storage
     base_dir: PathBuf,
+    /// Mailbox quota and free space checked before each message
+    storage: Option<Arc<StorageGuard>>,
 }
 
 impl VoicemailService {
@@ -296,9 +318,16 @@ impl VoicemailService {
     pub fn new<P: AsRef<Path>>(base_dir: P) -> Self {
         Self {
             base_dir: base_dir.as_ref().to_path_buf(),
+            storage: None,
         }
     }
 
+    /// Refuse messages the storage guard does not allow
+    pub fn with_storage_guard(mut self, storage: Arc<StorageGuard>) -> Self {
+        self.storage = Some(storage);
+        self
+    }
+
     /// Get directory for mailbox
     fn mailbox_dir(&self, mailbox_id: &str) -> PathBuf {
         self.base_dir.join(mailbox_id)
@@ -324,6 +353,35 @@ impl VoicemailService {
         VoicemailRecorder::new(mailbox.max_message_duration)
     }
 
+    /// Start a caller's message: a recorder, or the "mailbox full" prompt
+    /// when the mailbox or the disk has no room left
+    pub fn begin_deposit(&self, mailbox: &VoicemailMailbox) -> Deposit {
+        if let Some(storage) = &self.storage {
+            let dir = self.mailbox_dir(&mailbox.mailbox_id);
+            if let Err(reason) = storage.check_mailbox(&mailbox.mailbox_id, &dir) {
+                return Deposit::Refused {
+                    prompt: VoicemailPrompt::MailboxFull,
+                    reason,
+                };
+            }
+        }
+        Deposit::Record(self.create_recorder(mailbox))
+    }
+
+    /// Start a message to a distribution list, recorded with the sender's
+    /// length limit; refused when voicemail storage is full
+    pub fn begin_list_deposit(&self, sender: &VoicemailMailbox) -> Deposit {
+        if let Some(storage) = &self.storage {
+            if let Err(reason) = storage.check(StorageCategory::Voicemail) {
+                return Deposit::Refused {
+                    prompt: VoicemailPrompt::MailboxFull,
+                    reason,
+                };
+            }
+        }
+        Deposit::Record(self.create_recorder(sender))
+    }
+
     /// Save recording and create voicemail message
     pub fn save_recording(
         &self,
@@ -332,11 +390,75 @@ impl VoicemailService {
         caller_name: Option<String>,
         recorder: &VoicemailRecorder,
     ) -> Result<VoicemailMessage, String> {
-        // Ensure mailbox directory exists
+        self.save_to(mailbox_id, mailbox_id, caller, caller_name, recorder)
+    }
+
+    /// Save a message sent to a distribution list
+    ///
+    /// The file is stored once, under `lists/<list id>`; the message is the
+    /// template of the members' copies and is addressed to the list owner.
+    pub fn save_list_recording(
+        &self,
+        list: &VoicemailDistributionList,
+        caller: String,
+        caller_name: Option<String>,
+        recorder: &VoicemailRecorder,
+    ) -> Result<VoicemailMessage, String> {
+        let dir = format!("lists/{}", list.id);
+        let message = self.save_to(&dir, &list.owner, caller, caller_name, recorder)?;
+        Ok(message.with_via_list(list.name.clone()))
+    }
+
+    /// Copy of `message` for `mailbox_id`
+    ///
+    /// Without a comment the copy shares the recording. With one, the copy
+    /// gets its own file under the recipient's mailbox: the comment, then
+    /// the original message.
+    pub fn save_forward(
+        &self,
+        message: &VoicemailMessage,
+        mailbox_id: &str,
+        comment: Option<&VoicemailRecorder>,
+    ) -> Result<VoicemailMessage, String> {
+        let mut forwarded = message.forward_to(mailbox_id.to_string());
+        let comment = match comment.filter(|c| c.sample_count() > 0) {
+            Some(comment) => comment,
+            None => return Ok(forwarded),
+        };
+
+        let original = WavFile::from_file_converted(
+            self.base_dir.join(&message.audio_file_path),
+            NARROWBAND_RATE,
+        )
+        .map_err(|e| format!("Failed to load audio file: {}", e))?;
+        let comment_audio = WavFile::from_pcm(comment.sample_rate(), comment.samples());
+        let combined = WavFile::concat(&[comment_audio, original], NARROWBAND_RATE);
+
         self.ensure_mailbox_dir(mailbox_id)?;
+        let filename = self.generate_filename(mailbox_id);
+        fs::write(self.base_dir.join(&filename), combined.to_wav_bytes())
+            .map_err(|e| format!("Failed to write forwarded message: {}", e))?;
+
+        forwarded.audio_file_path = filename;
+        forwarded.audio_format = "wav".to_string();
+        forwarded.duration_seconds = combined.duration().round() as u32;
+        Ok(forwarded)
+    }
+
+    /// Write the recording under `dir` and create a message for `mailbox_id`
+    fn save_to(
+        &self,
+        dir: &str,
+        mailbox_id: &str,
+        caller: String,
+        caller_name: Option<String>,
+        recorder: &VoicemailRecorder,
+    ) -> Result<VoicemailMessage, String> {
+        // Ensure mailbox directory exists
+        self.ensure_mailbox_dir(dir)?;
 
         // Generate filename
-        let filename = self.generate_filename(mailbox_id);
+        let filename = self.generate_filename(dir);
         let full_path = self.base_dir.join(&filename);
 
         // Save recording
@@ -373,6 +495,20 @@ impl VoicemailService {
         Ok(())
     }
 
+    /// Delete a message, and its audio file unless other copies of a list
+    /// message still use it
+    pub async fn delete_message(
+        &self,
+        repository: &dyn VoicemailRepository,
+        message: &VoicemailMessage,
+    ) -> Result<(), String> {
+        repository.delete_message(message.id).await?;
+        if repository.count_audio_references(&message.audio_file_path).await? == 0 {
+            self.delete_audio_file(message)?;
+        }
+        Ok(())
+    }
+
     /// Get file size for message
     pub fn get_file_size(&self, message: &VoicemailMessage) -> Result<u64, String> {
         let path = self.base_dir.join(&message.audio_file_path);
@@ -534,4 +670,37 @@ mod tests {
         assert!(filename1.starts_with("alice/msg_"));
         assert!(filename1.ends_with(".wav"));
     }
+
+    #[test]
+    fn test_full_mailbox_plays_prompt_instead_of_recording() {
+        use crate::infrastructure::storage::{CategoryConfig, StorageConfig};
+        use crate::test_support::FakeFs;
+
+        let fs = Arc::new(FakeFs::new(10 << 30, 5 << 30));
+        fs.add_file("/var/voicemail/alice/msg_1.wav", 2 << 20, 1);
+        let storage = StorageGuard::with_fs(
+            StorageConfig {
+                voicemail: CategoryConfig {
+                    dir: Some(PathBuf::from("/var/voicemail")),
+                    ..Default::default()
+                },
+                mailbox_quota_mb: Some(2),
+                ..Default::default()
+            },
+            fs,
+        );
+        let service = VoicemailService::new("/var/voicemail").with_storage_guard(Arc::new(storage));
+
+        let alice = VoicemailMailbox::new("alice".to_string(), 1);
+        match service.begin_deposit(&alice) {
+            Deposit::Refused { prompt, reason } => {
+                assert_eq!(prompt, VoicemailPrompt::MailboxFull);
+                assert!(matches!(reason, StorageError::MailboxFull { .. }));
+            }
+            Deposit::Record(_) => panic!("full mailbox recorded a message"),
+        }
+
+        let bob = VoicemailMailbox::new("bob".to_string(), 2);
+        assert!(matches!(service.begin_deposit(&bob), Deposit::Record(_)));
+    }
 }
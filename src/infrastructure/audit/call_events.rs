@@ -0,0 +1,81 @@
+/// Audit records for call lifecycle events
+use super::logger::{AuditEvent, AuditEventType, AuditLevel, AuditLogger};
+use crate::application::events::EventBus;
+use crate::domain::call::{CallEvent, EndReason};
+use std::collections::HashMap;
+use std::sync::Arc;
+use tokio::sync::broadcast;
+use tokio::task::JoinHandle;
+use tracing::warn;
+
+/// Log initiated, answered and ended calls to the audit log
+///
+/// Answered and ended events do not name the parties; they are taken from
+/// the call's initiated event.
+pub fn spawn_call_audit(bus: &dyn EventBus, logger: Arc<AuditLogger>) -> JoinHandle<()> {
+    let mut rx = bus.subscribe();
+    tokio::spawn(async move {
+        // Caller and callee of calls in progress
+        let mut parties: HashMap<String, (String, String)> = HashMap::new();
+        loop {
+            let envelope = match rx.recv().await {
+                Ok(envelope) => envelope,
+                Err(broadcast::error::RecvError::Lagged(skipped)) => {
+                    warn!("Dropped {} call events (audit log lagging)", skipped);
+                    continue;
+                }
+                Err(broadcast::error::RecvError::Closed) => break,
+            };
+            let call_id = envelope.call_id;
+            let event_type = match envelope.event {
+                CallEvent::Initiated(e) => {
+                    let caller = e.caller.uri().to_string();
+                    let callee = e.callee.uri().to_string();
+                    parties.insert(call_id.clone(), (caller.clone(), callee.clone()));
+                    AuditEventType::CallInitiated {
+                        caller,
+                        callee,
+                        call_id,
+                    }
+                }
+                CallEvent::Answered(_) => {
+                    let (caller, callee) = parties.get(&call_id).cloned().unwrap_or_default();
+                    AuditEventType::CallAnswered {
+                        caller,
+                        callee,
+                        call_id,
+                    }
+                }
+                CallEvent::Ended(e) => {
+                    let (caller, callee) = parties.remove(&call_id).unwrap_or_default();
+                    match (e.duration_seconds, e.reason) {
+                        (Some(duration), _) => AuditEventType::CallTerminated {
+                            caller,
+                            callee,
+                            call_id,
+                            duration: duration.max(0) as u64,
+                        },
+                        (None, EndReason::Failed(reason)) => AuditEventType::CallFailed {
+                            caller,
+                            callee,
+                            reason,
+                        },
+                        (None, reason) => AuditEventType::CallFailed {
+                            caller,
+                            callee,
+                            reason: format!("{:?}", reason),
+                        },
+                    }
+                }
+                _ => continue,
+            };
+
+            let mut event = AuditEvent::new(AuditLevel::Info, event_type)
+                .with_metadata("event_id".to_string(), envelope.event_id.to_string());
+            if let Some(tenant_id) = envelope.tenant_id {
+                event = event.with_metadata("tenant_id".to_string(), tenant_id.to_string());
+            }
+            logger.log(event).await;
+        }
+    })
+}
@@ -1,4 +1,7 @@
 /// Audit logging for security events and compliance
+use crate::domain::privacy::{
+    ErasureSubject, Pseudonymizer, SubjectBearing, SubjectReference, SubjectStore,
+};
 use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
@@ -41,6 +44,8 @@ pub enum AuditEventType {
     CallAnswered { caller: String, callee: String, call_id: String },
     CallTerminated { caller: String, callee: String, call_id: String, duration: u64 },
     CallFailed { caller: String, callee: String, reason: String },
+    /// Media of a call leg switched to a new SRTP master key
+    SrtpKeyRotated { call_id: String, leg: String, crypto_tag: u32, profile: String },
 
     /// Conference events
     ConferenceCreated { name: String, created_by: String },
@@ -112,6 +117,66 @@ impl AuditEvent {
     }
 }
 
+impl SubjectBearing for AuditEvent {
+    const SUBJECT_FIELDS: &'static [&'static str] = &["username", "caller", "callee", "participant"];
+
+    fn subject_values(&self) -> Vec<(&'static str, &str)> {
+        use AuditEventType::*;
+        match &self.event_type {
+            AuthenticationSuccess { username, .. }
+            | AuthenticationLockout { username, .. }
+            | UserCreated { username, .. }
+            | UserUpdated { username, .. }
+            | UserDeleted { username, .. }
+            | UserPasswordChanged { username, .. }
+            | RoleAssigned { username, .. }
+            | ConferenceJoined { username, .. }
+            | ConferenceLeft { username, .. }
+            | DataExported { username, .. }
+            | DataDeleted { username, .. } => vec![("username", username.as_str())],
+            AuthenticationFailure { username: Some(username), .. }
+            | UnauthorizedAccess { username: Some(username), .. }
+            | SuspiciousActivity { username: Some(username), .. } => {
+                vec![("username", username.as_str())]
+            }
+            CallInitiated { caller, callee, .. }
+            | CallAnswered { caller, callee, .. }
+            | CallTerminated { caller, callee, .. }
+            | CallFailed { caller, callee, .. } => {
+                vec![("caller", caller.as_str()), ("callee", callee.as_str())]
+            }
+            ParticipantMuted { participant, .. } => vec![("participant", participant.as_str())],
+            _ => Vec::new(),
+        }
+    }
+
+    fn subject_values_mut(&mut self) -> Vec<(&'static str, &mut String)> {
+        use AuditEventType::*;
+        match &mut self.event_type {
+            AuthenticationSuccess { username, .. }
+            | AuthenticationLockout { username, .. }
+            | UserCreated { username, .. }
+            | UserUpdated { username, .. }
+            | UserDeleted { username, .. }
+            | UserPasswordChanged { username, .. }
+            | RoleAssigned { username, .. }
+            | ConferenceJoined { username, .. }
+            | ConferenceLeft { username, .. }
+            | DataExported { username, .. }
+            | DataDeleted { username, .. } => vec![("username", username)],
+            AuthenticationFailure { username: Some(username), .. }
+            | UnauthorizedAccess { username: Some(username), .. }
+            | SuspiciousActivity { username: Some(username), .. } => vec![("username", username)],
+            CallInitiated { caller, callee, .. }
+            | CallAnswered { caller, callee, .. }
+            | CallTerminated { caller, callee, .. }
+            | CallFailed { caller, callee, .. } => vec![("caller", caller), ("callee", callee)],
+            ParticipantMuted { participant, .. } => vec![("participant", participant)],
+            _ => Vec::new(),
+        }
+    }
+}
+
 /// Audit logger backend trait
 #[async_trait::async_trait]
 pub trait AuditBackend: Send + Sync {
@@ -206,6 +271,44 @@ impl AuditBackend for MemoryAuditBackend {
     }
 }
 
+#[async_trait::async_trait]
+impl SubjectStore for MemoryAuditBackend {
+    fn name(&self) -> &'static str {
+        "audit_log"
+    }
+
+    fn subject_fields(&self) -> &'static [&'static str] {
+        AuditEvent::SUBJECT_FIELDS
+    }
+
+    async fn find_subject(&self, subject: &ErasureSubject) -> Result<Vec<SubjectReference>, String> {
+        let events = self.events.read().await;
+        Ok(events
+            .iter()
+            .filter_map(|e| SubjectReference::to(e, e.id, e.timestamp, subject))
+            .collect())
+    }
+
+    async fn pseudonymize(
+        &self,
+        subject: &ErasureSubject,
+        pseudonyms: &Pseudonymizer,
+        records: &[SubjectReference],
+    ) -> Result<u64, String> {
+        let mut events = self.events.write().await;
+        let mut count = 0;
+        for event in events
+            .iter_mut()
+            .filter(|e| records.iter().any(|r| r.record_id == e.id.to_string()))
+        {
+            if event.pseudonymize(subject, pseudonyms) {
+                count += 1;
+            }
+        }
+        Ok(count)
+    }
+}
+
 /// Main audit logger
 pub struct AuditLogger {
     backend: Arc<dyn AuditBackend>,
@@ -301,6 +404,26 @@ impl AuditLogger {
         self.log(event).await;
     }
 
+    pub async fn log_srtp_key_rotation(
+        &self,
+        call_id: String,
+        leg: String,
+        crypto_tag: u32,
+        profile: String,
+    ) {
+        let event = AuditEvent::new(
+            AuditLevel::Info,
+            AuditEventType::SrtpKeyRotated {
+                call_id: call_id.clone(),
+                leg,
+                crypto_tag,
+                profile,
+            },
+        )
+        .with_session(call_id);
+        self.log(event).await;
+    }
+
     pub async fn log_unauthorized_access(
         &self,
         resource: String,
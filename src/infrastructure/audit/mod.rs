@@ -1,4 +1,6 @@
 /// Audit logging system for security and compliance
+pub mod call_events;
 pub mod logger;
 
+pub use call_events::spawn_call_audit;
 pub use logger::{AuditLogger, AuditEvent, AuditEventType, AuditLevel};
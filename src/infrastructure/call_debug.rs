@@ -0,0 +1,486 @@
+//! Per-call debug logging
+//!
+//! Debug logging can be switched on for a single call at runtime instead of
+//! raising the global log level. Every event of an armed call, at any
+//! level, is captured into a ring buffer of its own and, with a
+//! `mirror_dir`, appended to `<mirror_dir>/<call-id>.log`. An event belongs
+//! to a call when it carries a `call_id` field or is emitted inside a span
+//! that does (the SIP server opens one per message). Users can be pre-armed
+//! so their next calls are debugged as they start. Calls are disarmed when
+//! they end; pre-armed users expire after a TTL.
+
+use super::logging::{format_event, LogRingBuffer};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, VecDeque};
+use std::fmt;
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::Duration;
+use tracing::field::{Field, Visit};
+use tracing::span::{Attributes, Id};
+use tracing::subscriber::Interest;
+use tracing::{info, warn, Event, Level, Metadata, Subscriber};
+use tracing_subscriber::layer::{Context, Layer};
+use tracing_subscriber::registry::LookupSpan;
+
+/// Field naming the call an event or span belongs to
+pub const CALL_ID_FIELD: &str = "call_id";
+
+/// Per-call debug logging settings
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct CallDebugConfig {
+    /// Lines kept per debugged call
+    pub buffer_lines: usize,
+    /// Directory the lines of debugged calls are mirrored to, one file per
+    /// Call-ID
+    pub mirror_dir: Option<PathBuf>,
+    /// How long a pre-armed user stays armed when no TTL is given
+    pub prearm_ttl_secs: u64,
+    /// Buffers of ended calls kept for retrieval
+    pub kept_buffers: usize,
+}
+
+impl Default for CallDebugConfig {
+    fn default() -> Self {
+        Self {
+            buffer_lines: 2000,
+            mirror_dir: None,
+            prearm_ttl_secs: 3600,
+            kept_buffers: 20,
+        }
+    }
+}
+
+/// A debugged call
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DebugCall {
+    pub call_id: String,
+    pub armed_at: DateTime<Utc>,
+    /// Pre-armed user the call was armed for, `None` when armed directly
+    pub user: Option<String>,
+    /// Lines captured so far
+    pub lines: usize,
+}
+
+/// A user whose next calls get debugged
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DebugUser {
+    pub username: String,
+    pub remaining_calls: u32,
+    pub expires_at: DateTime<Utc>,
+}
+
+/// Everything currently debug-enabled
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DebugTargets {
+    pub calls: Vec<DebugCall>,
+    pub users: Vec<DebugUser>,
+}
+
+/// Capture state of one debugged call
+struct DebugSession {
+    armed_at: DateTime<Utc>,
+    user: Option<String>,
+    buffer: LogRingBuffer,
+    mirror: Option<Mutex<File>>,
+}
+
+/// Calls and users with debug logging enabled
+pub struct CallDebugRegistry {
+    config: CallDebugConfig,
+    /// Number of armed calls, so events are dismissed without locking
+    /// while nothing is armed
+    armed: AtomicUsize,
+    calls: RwLock<HashMap<String, Arc<DebugSession>>>,
+    users: Mutex<HashMap<String, DebugUser>>,
+    /// Sessions of ended calls, oldest first
+    finished: Mutex<VecDeque<(String, Arc<DebugSession>)>>,
+}
+
+impl CallDebugRegistry {
+    pub fn new(config: CallDebugConfig) -> Self {
+        Self {
+            config,
+            armed: AtomicUsize::new(0),
+            calls: RwLock::new(HashMap::new()),
+            users: Mutex::new(HashMap::new()),
+            finished: Mutex::new(VecDeque::new()),
+        }
+    }
+
+    /// Tracing layer capturing the events of debugged calls
+    ///
+    /// The layer must not sit behind a global level filter: it enables
+    /// debug and trace events itself while a call is armed. Other layers
+    /// should filter their own level (`Layer::with_filter`).
+    pub fn layer(self: &Arc<Self>) -> CallDebugLayer {
+        CallDebugLayer {
+            registry: self.clone(),
+        }
+    }
+
+    /// Whether any call is being debugged
+    pub fn any_armed(&self) -> bool {
+        self.armed.load(Ordering::Relaxed) > 0
+    }
+
+    pub fn is_armed(&self, call_id: &str) -> bool {
+        self.any_armed() && self.calls.read().unwrap().contains_key(call_id)
+    }
+
+    /// Start debugging a call; false when it already was
+    pub fn arm_call(&self, call_id: &str) -> bool {
+        let armed = self.arm(call_id, None);
+        if armed {
+            info!(call_id, "Debug logging enabled for call");
+        }
+        armed
+    }
+
+    fn arm(&self, call_id: &str, user: Option<String>) -> bool {
+        if self.calls.read().unwrap().contains_key(call_id) {
+            return false;
+        }
+        // Opened before locking: a failure is logged, which reads the calls
+        let mirror = self.open_mirror(call_id).map(Mutex::new);
+        let mut calls = self.calls.write().unwrap();
+        if calls.contains_key(call_id) {
+            return false;
+        }
+        let session = DebugSession {
+            armed_at: Utc::now(),
+            user,
+            buffer: LogRingBuffer::new(self.config.buffer_lines),
+            mirror,
+        };
+        calls.insert(call_id.to_string(), Arc::new(session));
+        self.armed.store(calls.len(), Ordering::Relaxed);
+        true
+    }
+
+    /// Stop debugging a call, keeping its lines for retrieval; false when
+    /// it was not armed
+    pub fn disarm_call(&self, call_id: &str) -> bool {
+        let session = {
+            let mut calls = self.calls.write().unwrap();
+            let session = calls.remove(call_id);
+            self.armed.store(calls.len(), Ordering::Relaxed);
+            session
+        };
+        let Some(session) = session else {
+            return false;
+        };
+
+        let mut finished = self.finished.lock().unwrap();
+        finished.retain(|(id, _)| id != call_id);
+        finished.push_back((call_id.to_string(), session));
+        while finished.len() > self.config.kept_buffers {
+            finished.pop_front();
+        }
+        drop(finished);
+        info!(call_id, "Debug logging disabled for call");
+        true
+    }
+
+    /// Debug the next `next_calls` calls of `username`, for `ttl` (default
+    /// `prearm_ttl_secs`)
+    pub fn arm_user(&self, username: &str, next_calls: u32, ttl: Option<Duration>) -> DebugUser {
+        let ttl = ttl.unwrap_or(Duration::from_secs(self.config.prearm_ttl_secs));
+        let user = DebugUser {
+            username: username.to_string(),
+            remaining_calls: next_calls,
+            expires_at: Utc::now()
+                + chrono::Duration::from_std(ttl).unwrap_or_else(|_| chrono::Duration::hours(1)),
+        };
+        self.users
+            .lock()
+            .unwrap()
+            .insert(username.to_string(), user.clone());
+        info!(
+            "Debug logging pre-armed for the next {} calls of {}",
+            next_calls, username
+        );
+        user
+    }
+
+    /// A call of `usernames` (caller and callee) started: arm it when one
+    /// of them is pre-armed
+    pub fn call_started(&self, call_id: &str, usernames: &[&str]) -> bool {
+        let user = {
+            let mut users = self.users.lock().unwrap();
+            let now = Utc::now();
+            users.retain(|_, user| user.expires_at > now && user.remaining_calls > 0);
+            let Some(username) = usernames.iter().find(|name| users.contains_key(**name)) else {
+                return false;
+            };
+            if let Some(user) = users.get_mut(*username) {
+                user.remaining_calls -= 1;
+                if user.remaining_calls == 0 {
+                    users.remove(*username);
+                }
+            }
+            username.to_string()
+        };
+        let armed = self.arm(call_id, Some(user.clone()));
+        if armed {
+            info!(call_id, "Debug logging enabled for call of {}", user);
+        }
+        armed
+    }
+
+    /// Lines captured for a call, armed or recently ended
+    pub fn lines(&self, call_id: &str) -> Option<Vec<String>> {
+        let session = self
+            .calls
+            .read()
+            .unwrap()
+            .get(call_id)
+            .cloned()
+            .or_else(|| {
+                self.finished
+                    .lock()
+                    .unwrap()
+                    .iter()
+                    .find(|(id, _)| id == call_id)
+                    .map(|(_, session)| session.clone())
+            })?;
+        Some(session.buffer.tail(session.buffer.len()))
+    }
+
+    /// Armed calls and pre-armed users
+    pub fn targets(&self) -> DebugTargets {
+        let mut calls: Vec<DebugCall> = self
+            .calls
+            .read()
+            .unwrap()
+            .iter()
+            .map(|(call_id, session)| DebugCall {
+                call_id: call_id.clone(),
+                armed_at: session.armed_at,
+                user: session.user.clone(),
+                lines: session.buffer.len(),
+            })
+            .collect();
+        calls.sort_by_key(|c| c.armed_at);
+
+        let now = Utc::now();
+        let mut users = self.users.lock().unwrap();
+        users.retain(|_, user| user.expires_at > now);
+        let mut users: Vec<DebugUser> = users.values().cloned().collect();
+        users.sort_by(|a, b| a.username.cmp(&b.username));
+        DebugTargets { calls, users }
+    }
+
+    /// Record an event of `call_id` if the call is armed
+    fn capture(&self, call_id: &str, event: &Event<'_>) {
+        let Some(session) = self.calls.read().unwrap().get(call_id).cloned() else {
+            return;
+        };
+        let line = format_event(event);
+        if let Some(mirror) = &session.mirror {
+            // Logging a failure here would recurse into the layer
+            let _ = writeln!(mirror.lock().unwrap(), "{}", line);
+        }
+        session.buffer.push(line);
+    }
+
+    fn open_mirror(&self, call_id: &str) -> Option<File> {
+        let dir = self.config.mirror_dir.as_ref()?;
+        let path = dir.join(format!("{}.log", file_name(call_id)));
+        let file = std::fs::create_dir_all(dir)
+            .and_then(|_| OpenOptions::new().create(true).append(true).open(&path));
+        match file {
+            Ok(file) => Some(file),
+            Err(e) => {
+                warn!("Cannot mirror debug log to {}: {}", path.display(), e);
+                None
+            }
+        }
+    }
+}
+
+impl Default for CallDebugRegistry {
+    fn default() -> Self {
+        Self::new(CallDebugConfig::default())
+    }
+}
+
+/// Call-ID made safe as a file name
+fn file_name(call_id: &str) -> String {
+    call_id
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || "@.-_".contains(c) {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect()
+}
+
+/// Call-ID of a span, kept in its extensions
+struct SpanCallId(String);
+
+/// Tracing layer feeding a [`CallDebugRegistry`]
+pub struct CallDebugLayer {
+    registry: Arc<CallDebugRegistry>,
+}
+
+impl<S> Layer<S> for CallDebugLayer
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+{
+    fn register_callsite(&self, metadata: &'static Metadata<'static>) -> Interest {
+        // Verbose events are decided per event, as calls get armed
+        if metadata.is_span() || *metadata.level() <= Level::INFO {
+            Interest::always()
+        } else {
+            Interest::sometimes()
+        }
+    }
+
+    fn enabled(&self, metadata: &Metadata<'_>, _ctx: Context<'_, S>) -> bool {
+        metadata.is_span() || *metadata.level() <= Level::INFO || self.registry.any_armed()
+    }
+
+    fn on_new_span(&self, attrs: &Attributes<'_>, id: &Id, ctx: Context<'_, S>) {
+        let mut visitor = CallIdVisitor::default();
+        attrs.record(&mut visitor);
+        if let (Some(call_id), Some(span)) = (visitor.0, ctx.span(id)) {
+            span.extensions_mut().insert(SpanCallId(call_id));
+        }
+    }
+
+    fn on_event(&self, event: &Event<'_>, ctx: Context<'_, S>) {
+        if !self.registry.any_armed() {
+            return;
+        }
+        let mut visitor = CallIdVisitor::default();
+        event.record(&mut visitor);
+        let call_id = visitor.0.or_else(|| {
+            ctx.event_scope(event)?.find_map(|span| {
+                span.extensions()
+                    .get::<SpanCallId>()
+                    .map(|call_id| call_id.0.clone())
+            })
+        });
+        if let Some(call_id) = call_id {
+            self.registry.capture(&call_id, event);
+        }
+    }
+}
+
+/// Picks the `call_id` field of an event or span
+#[derive(Default)]
+struct CallIdVisitor(Option<String>);
+
+impl Visit for CallIdVisitor {
+    fn record_str(&mut self, field: &Field, value: &str) {
+        if field.name() == CALL_ID_FIELD {
+            self.0 = Some(value.to_string());
+        }
+    }
+
+    fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+        if field.name() == CALL_ID_FIELD {
+            self.0 = Some(format!("{:?}", value).trim_matches('"').to_string());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tracing::Instrument;
+    use tracing_subscriber::filter::LevelFilter;
+    use tracing_subscriber::prelude::*;
+
+    /// One call's signaling: a span with its Call-ID and detailed events
+    async fn simulated_call(call_id: &'static str) {
+        async move {
+            for step in 0..3 {
+                tracing::debug!("processing step {} of {}", step, call_id);
+                tokio::task::yield_now().await;
+            }
+            tracing::trace!(payload = "sdp", "offer of {}", call_id);
+            tracing::info!("call {} answered", call_id);
+        }
+        .instrument(tracing::info_span!("sip", call_id))
+        .await
+    }
+
+    #[tokio::test]
+    async fn test_debug_one_of_two_concurrent_calls() {
+        let registry = Arc::new(CallDebugRegistry::default());
+        let global = Arc::new(LogRingBuffer::new(100));
+        let subscriber = tracing_subscriber::registry()
+            .with(global.layer().with_filter(LevelFilter::INFO))
+            .with(registry.layer());
+        let _guard = tracing::subscriber::set_default(subscriber);
+
+        assert!(registry.arm_call("call-a"));
+        assert!(!registry.arm_call("call-a"));
+        tokio::join!(simulated_call("call-a"), simulated_call("call-b"));
+        // Events naming the call directly count too
+        tracing::debug!(call_id = "call-a", "bridge closed");
+
+        let lines = registry.lines("call-a").unwrap();
+        assert_eq!(
+            lines
+                .iter()
+                .filter(|l| l.contains("processing step"))
+                .count(),
+            3
+        );
+        assert!(lines
+            .iter()
+            .any(|l| l.contains("TRACE") && l.contains("payload=sdp")));
+        assert!(lines.iter().any(|l| l.contains("call call-a answered")));
+        assert!(lines.iter().any(|l| l.contains("bridge closed")));
+        assert!(lines.iter().all(|l| !l.contains("call-b")));
+        assert!(registry.lines("call-b").is_none());
+
+        // The global filter still applies everywhere else
+        let global = global.tail(100);
+        assert!(global
+            .iter()
+            .all(|l| !l.contains("DEBUG") && !l.contains("TRACE")));
+        assert!(global.iter().any(|l| l.contains("call call-b answered")));
+
+        // Ended calls keep their lines but capture nothing more
+        assert!(registry.disarm_call("call-a"));
+        assert!(!registry.any_armed());
+        tracing::debug!(call_id = "call-a", "late event");
+        let lines = registry.lines("call-a").unwrap();
+        assert!(lines.iter().all(|l| !l.contains("late event")));
+    }
+
+    #[test]
+    fn test_prearmed_user() {
+        let registry = CallDebugRegistry::default();
+        registry.arm_user("alice", 1, None);
+        assert_eq!(registry.targets().users.len(), 1);
+
+        assert!(!registry.call_started("call-1", &["bob", "carol"]));
+        assert!(registry.call_started("call-2", &["bob", "alice"]));
+        assert!(registry.is_armed("call-2"));
+        // Only the next call
+        assert!(!registry.call_started("call-3", &["alice", "bob"]));
+
+        let targets = registry.targets();
+        assert!(targets.users.is_empty());
+        assert_eq!(targets.calls.len(), 1);
+        assert_eq!(targets.calls[0].user.as_deref(), Some("alice"));
+
+        // Expired pre-arms are dropped
+        registry.arm_user("dave", 5, Some(Duration::ZERO));
+        assert!(!registry.call_started("call-4", &["dave"]));
+        assert!(registry.targets().users.is_empty());
+    }
+}
@@ -0,0 +1,38 @@
+//! Time source for timer-driven logic
+//!
+//! The SIP transaction layer and the registrar read the time through a
+//! [`Clock`], so tests can replace the system clock with one they advance
+//! by hand (`test_support::ManualClock`, behind the `test-support` feature)
+//! instead of sleeping through timers and binding expiry.
+
+use chrono::{DateTime, Utc};
+use std::sync::Arc;
+use tokio::time::Instant;
+
+/// Wall clock and monotonic time
+pub trait Clock: Send + Sync {
+    /// Current wall-clock time, e.g. for binding expiry
+    fn now(&self) -> DateTime<Utc>;
+
+    /// Current monotonic time, e.g. for transaction timers
+    fn instant(&self) -> Instant;
+}
+
+/// The system clock
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> DateTime<Utc> {
+        Utc::now()
+    }
+
+    fn instant(&self) -> Instant {
+        Instant::now()
+    }
+}
+
+/// Shared system clock, the default of everything taking a [`Clock`]
+pub fn system_clock() -> Arc<dyn Clock> {
+    Arc::new(SystemClock)
+}
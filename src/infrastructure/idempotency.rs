@@ -0,0 +1,407 @@
+//! Idempotency keys of retried API requests
+//!
+//! A client retrying a mutating request after a lost response sends the
+//! same `Idempotency-Key` again. [`IdempotencyCache`] keeps the response of
+//! every key for `ttl_secs`: a repeat of the same request is answered from
+//! it without running again, the key on a different request is refused. A
+//! repeat arriving while the first request still runs waits up to
+//! `wait_ms` for its response. Responses are written through an
+//! [`IdempotencyRepository`] when there is one, so replays survive a
+//! restart. Server errors are not kept; the key can be retried.
+//!
+//! Side effects that cannot be made idempotent, such as a SIP request sent
+//! before the handler failed, are claimed with [`Idempotency::first`].
+
+use crate::infrastructure::clock::{system_clock, Clock};
+use async_trait::async_trait;
+use chrono::{DateTime, Duration, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use tokio::sync::watch;
+use tokio::time::Instant;
+use tracing::{debug, warn};
+
+/// Idempotency key settings
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct IdempotencyConfig {
+    pub enabled: bool,
+    /// Seconds a response is replayed for; after that the key is new again
+    pub ttl_secs: u64,
+    /// Responses kept in memory; the oldest go first
+    pub max_entries: usize,
+    /// Milliseconds a repeat waits for the first request with its key
+    /// before it is refused with 409
+    pub wait_ms: u64,
+}
+
+impl Default for IdempotencyConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            ttl_secs: 86_400,
+            max_entries: 10_000,
+            wait_ms: 5_000,
+        }
+    }
+}
+
+impl IdempotencyConfig {
+    pub fn validate(&self) -> Result<(), String> {
+        if self.ttl_secs == 0 {
+            return Err("ttl_secs must be at least 1".to_string());
+        }
+        if self.max_entries == 0 {
+            return Err("max_entries must be at least 1".to_string());
+        }
+        if self.wait_ms > 60_000 {
+            return Err("wait_ms must be at most 60000".to_string());
+        }
+        Ok(())
+    }
+}
+
+/// Response kept for replay
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct StoredResponse {
+    /// Hash of the request the key was first used with
+    pub fingerprint: String,
+    pub status: u16,
+    pub content_type: Option<String>,
+    pub body: Vec<u8>,
+    pub expires_at: DateTime<Utc>,
+}
+
+/// Durable storage of kept responses
+#[async_trait]
+pub trait IdempotencyRepository: Send + Sync {
+    /// Response kept for `key`, expired or not
+    async fn get(&self, key: &str) -> Result<Option<StoredResponse>, String>;
+
+    async fn save(&self, key: &str, response: &StoredResponse) -> Result<(), String>;
+
+    /// Drop responses expired before `now`; returns how many
+    async fn purge_expired(&self, now: DateTime<Utc>) -> Result<u64, String>;
+}
+
+/// What to do with a request carrying a key
+#[derive(Debug, Clone, PartialEq)]
+pub enum Begin {
+    /// First request with the key: run it, then `complete` or `abandon`
+    Execute,
+    /// Same request answered before
+    Replay(StoredResponse),
+    /// The key was used with a different request
+    Mismatch,
+    /// The first request with the key is still running
+    InProgress,
+}
+
+enum Entry {
+    /// Dropping `done` wakes the requests waiting for it
+    Running {
+        fingerprint: String,
+        done: watch::Sender<()>,
+    },
+    Done(StoredResponse),
+}
+
+/// Responses by idempotency key
+pub struct IdempotencyCache {
+    config: IdempotencyConfig,
+    entries: Mutex<HashMap<String, Entry>>,
+    /// Expiry of side effects claimed, by key and effect
+    effects: Mutex<HashMap<(String, String), DateTime<Utc>>>,
+    repository: Option<Arc<dyn IdempotencyRepository>>,
+    clock: Arc<dyn Clock>,
+}
+
+impl IdempotencyCache {
+    pub fn new(config: IdempotencyConfig) -> Self {
+        Self {
+            config,
+            entries: Mutex::new(HashMap::new()),
+            effects: Mutex::new(HashMap::new()),
+            repository: None,
+            clock: system_clock(),
+        }
+    }
+
+    /// Keep responses in `repository` as well
+    pub fn with_repository(mut self, repository: Arc<dyn IdempotencyRepository>) -> Self {
+        self.repository = Some(repository);
+        self
+    }
+
+    pub fn with_clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    pub fn config(&self) -> &IdempotencyConfig {
+        &self.config
+    }
+
+    fn answer(stored: &StoredResponse, fingerprint: &str) -> Begin {
+        if stored.fingerprint == fingerprint {
+            Begin::Replay(stored.clone())
+        } else {
+            Begin::Mismatch
+        }
+    }
+
+    /// Start a request with `key`; `fingerprint` tells repeats of the same
+    /// request from other requests reusing the key
+    pub async fn begin(&self, key: &str, fingerprint: &str) -> Begin {
+        let deadline = Instant::now() + std::time::Duration::from_millis(self.config.wait_ms);
+        loop {
+            let now = self.clock.now();
+            let mut done = {
+                let mut entries = self.entries.lock().unwrap();
+                match entries.get(key) {
+                    Some(Entry::Done(stored)) if stored.expires_at > now => {
+                        return Self::answer(stored, fingerprint);
+                    }
+                    Some(Entry::Running { fingerprint: running, .. }) if running != fingerprint => {
+                        return Begin::Mismatch;
+                    }
+                    Some(Entry::Running { done, .. }) => done.subscribe(),
+                    _ => {
+                        entries.insert(
+                            key.to_string(),
+                            Entry::Running {
+                                fingerprint: fingerprint.to_string(),
+                                done: watch::channel(()).0,
+                            },
+                        );
+                        break;
+                    }
+                }
+            };
+            if tokio::time::timeout_at(deadline, done.changed()).await.is_err() {
+                return Begin::InProgress;
+            }
+        }
+
+        // Not in memory: answered before a restart?
+        let Some(repository) = &self.repository else {
+            return Begin::Execute;
+        };
+        match repository.get(key).await {
+            Ok(Some(stored)) if stored.expires_at > self.clock.now() => {
+                let begin = Self::answer(&stored, fingerprint);
+                self.settle(key, Some(stored));
+                begin
+            }
+            Ok(_) => Begin::Execute,
+            Err(e) => {
+                warn!("Failed to look up idempotency key: {}", e);
+                Begin::Execute
+            }
+        }
+    }
+
+    /// Replace the entry of `key`, waking requests waiting for it
+    fn settle(&self, key: &str, stored: Option<StoredResponse>) {
+        let mut entries = self.entries.lock().unwrap();
+        match stored {
+            Some(stored) => {
+                self.make_room(&mut entries);
+                entries.insert(key.to_string(), Entry::Done(stored));
+            }
+            None => {
+                entries.remove(key);
+            }
+        }
+    }
+
+    /// Drop expired responses, then the oldest, to stay under `max_entries`
+    fn make_room(&self, entries: &mut HashMap<String, Entry>) {
+        if entries.len() < self.config.max_entries {
+            return;
+        }
+        let now = self.clock.now();
+        entries.retain(|_, entry| !matches!(entry, Entry::Done(stored) if stored.expires_at <= now));
+        while entries.len() >= self.config.max_entries {
+            let oldest = entries
+                .iter()
+                .filter_map(|(key, entry)| match entry {
+                    Entry::Done(stored) => Some((key.clone(), stored.expires_at)),
+                    Entry::Running { .. } => None,
+                })
+                .min_by_key(|(_, expires_at)| *expires_at);
+            match oldest {
+                Some((key, _)) => {
+                    entries.remove(&key);
+                }
+                None => break,
+            }
+        }
+    }
+
+    /// Keep the response of the request started with `key`
+    pub async fn complete(
+        &self,
+        key: &str,
+        fingerprint: &str,
+        status: u16,
+        content_type: Option<String>,
+        body: Vec<u8>,
+    ) {
+        let stored = StoredResponse {
+            fingerprint: fingerprint.to_string(),
+            status,
+            content_type,
+            body,
+            expires_at: self.clock.now() + Duration::seconds(self.config.ttl_secs as i64),
+        };
+        if let Some(repository) = &self.repository {
+            if let Err(e) = repository.save(key, &stored).await {
+                warn!("Failed to persist idempotency key: {}", e);
+            }
+        }
+        self.settle(key, Some(stored));
+    }
+
+    /// Forget the request started with `key`, so it can be retried
+    pub fn abandon(&self, key: &str) {
+        debug!("Idempotency key released without a response");
+        self.settle(key, None);
+    }
+
+    /// Whether `effect` is claimed for `key` for the first time
+    pub fn claim(&self, key: &str, effect: &str) -> bool {
+        let now = self.clock.now();
+        let mut effects = self.effects.lock().unwrap();
+        effects.retain(|_, expires_at| *expires_at > now);
+        let claim = (key.to_string(), effect.to_string());
+        if effects.contains_key(&claim) {
+            return false;
+        }
+        effects.insert(claim, now + Duration::seconds(self.config.ttl_secs as i64));
+        true
+    }
+
+    /// Drop expired responses from memory and the repository
+    pub async fn purge_expired(&self) -> u64 {
+        let now = self.clock.now();
+        let mut purged = {
+            let mut entries = self.entries.lock().unwrap();
+            let before = entries.len();
+            entries.retain(|_, entry| !matches!(entry, Entry::Done(stored) if stored.expires_at <= now));
+            (before - entries.len()) as u64
+        };
+        if let Some(repository) = &self.repository {
+            match repository.purge_expired(now).await {
+                Ok(count) => purged = purged.max(count),
+                Err(e) => warn!("Failed to purge idempotency keys: {}", e),
+            }
+        }
+        purged
+    }
+
+    /// Purge expired responses every `interval`
+    pub fn spawn_purge(self: Arc<Self>, interval: std::time::Duration) -> tokio::task::JoinHandle<()> {
+        tokio::spawn(async move {
+            let mut ticker = tokio::time::interval(interval);
+            loop {
+                ticker.tick().await;
+                let purged = self.purge_expired().await;
+                if purged > 0 {
+                    debug!("Purged {} expired idempotency keys", purged);
+                }
+            }
+        })
+    }
+}
+
+/// Idempotency key of the request being handled, as a request extension
+#[derive(Clone)]
+pub struct Idempotency {
+    pub key: String,
+    cache: Arc<IdempotencyCache>,
+}
+
+impl Idempotency {
+    pub fn new(key: String, cache: Arc<IdempotencyCache>) -> Self {
+        Self { key, cache }
+    }
+
+    /// Whether `effect` has not been carried out under this key yet; claims
+    /// it. A handler retried after failing part-way skips what it did.
+    pub fn first(&self, effect: &str) -> bool {
+        self.cache.claim(&self.key, effect)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::ManualClock;
+
+    fn cache(clock: Arc<ManualClock>) -> Arc<IdempotencyCache> {
+        Arc::new(
+            IdempotencyCache::new(IdempotencyConfig {
+                ttl_secs: 60,
+                max_entries: 2,
+                wait_ms: 200,
+                ..Default::default()
+            })
+            .with_clock(clock),
+        )
+    }
+
+    #[tokio::test]
+    async fn test_replay_until_expiry() {
+        let clock = Arc::new(ManualClock::new());
+        let cache = cache(clock.clone());
+
+        assert_eq!(cache.begin("k1", "a").await, Begin::Execute);
+        cache.complete("k1", "a", 201, None, b"done".to_vec()).await;
+        match cache.begin("k1", "a").await {
+            Begin::Replay(stored) => {
+                assert_eq!(stored.status, 201);
+                assert_eq!(stored.body, b"done");
+            }
+            other => panic!("expected a replay, got {:?}", other),
+        }
+        assert_eq!(cache.begin("k1", "b").await, Begin::Mismatch);
+
+        // A server error leaves the key free
+        assert_eq!(cache.begin("k2", "a").await, Begin::Execute);
+        cache.abandon("k2");
+        assert_eq!(cache.begin("k2", "a").await, Begin::Execute);
+
+        clock.advance(std::time::Duration::from_secs(61));
+        assert_eq!(cache.begin("k1", "b").await, Begin::Execute);
+    }
+
+    #[tokio::test]
+    async fn test_concurrent_repeat_waits_for_the_first() {
+        let clock = Arc::new(ManualClock::new());
+        let cache = cache(clock);
+
+        assert_eq!(cache.begin("k", "a").await, Begin::Execute);
+        let waiting = tokio::spawn({
+            let cache = cache.clone();
+            async move { cache.begin("k", "a").await }
+        });
+        tokio::task::yield_now().await;
+        cache.complete("k", "a", 200, None, b"ok".to_vec()).await;
+        assert!(matches!(waiting.await.unwrap(), Begin::Replay(_)));
+
+        assert_eq!(cache.begin("slow", "a").await, Begin::Execute);
+        assert_eq!(cache.begin("slow", "a").await, Begin::InProgress);
+    }
+
+    #[test]
+    fn test_effects_are_claimed_once() {
+        let cache = Arc::new(IdempotencyCache::new(IdempotencyConfig::default()));
+        let idempotency = Idempotency::new("k".to_string(), cache.clone());
+        assert!(idempotency.first("refer"));
+        assert!(!idempotency.first("refer"));
+        assert!(idempotency.first("bye"));
+        assert!(Idempotency::new("other".to_string(), cache).first("refer"));
+    }
+}
@@ -0,0 +1,236 @@
+/// Extension and name directory of IVR menus
+///
+/// Extension-collection menus check the dialed digits against it and
+/// dial-by-name menus search it by the keypad digits of a surname.
+use crate::domain::user::{DirectoryQuery, User, UserRepository, MAX_DIRECTORY_LIMIT};
+use crate::domain::voicemail::VoicemailRepository;
+use async_trait::async_trait;
+use std::sync::Arc;
+use tracing::warn;
+
+/// Prompt asking for another letter when too many names match
+pub const MORE_LETTERS_PROMPT: &str = "ivr/dbn_more_letters.wav";
+/// Prompt played when no name matches
+pub const NO_MATCH_PROMPT: &str = "ivr/dbn_no_match.wav";
+/// "Press" before the key of a match
+pub const PRESS_PROMPT: &str = "ivr/dbn_press.wav";
+/// "Press 1 to connect, star to start over" after a single match
+pub const CONFIRM_PROMPT: &str = "ivr/dbn_confirm.wav";
+
+/// Prompt of a digit or keypad symbol
+pub fn digit_prompt(digit: char) -> String {
+    match digit {
+        '*' => "ivr/digits/star.wav".to_string(),
+        '#' => "ivr/digits/pound.wav".to_string(),
+        digit => format!("ivr/digits/{}.wav", digit),
+    }
+}
+
+/// Prompt of a letter, for names spelled out
+pub fn letter_prompt(letter: char) -> String {
+    format!("ivr/letters/{}.wav", letter.to_ascii_lowercase())
+}
+
+/// Key carrying `letter` on a phone keypad (ITU E.161)
+pub fn keypad_digit(letter: char) -> Option<char> {
+    let digit = match letter.to_ascii_uppercase() {
+        'A'..='C' => '2',
+        'D'..='F' => '3',
+        'G'..='I' => '4',
+        'J'..='L' => '5',
+        'M'..='O' => '6',
+        'P'..='S' => '7',
+        'T'..='V' => '8',
+        'W'..='Z' => '9',
+        _ => return None,
+    };
+    Some(digit)
+}
+
+/// Keys spelling the letters of `text`; other characters are skipped, so
+/// "O'Brien" is keyed as OBRIEN
+pub fn keypad_digits(text: &str) -> String {
+    text.chars().filter_map(keypad_digit).collect()
+}
+
+/// Last word of a name
+pub fn surname(name: &str) -> &str {
+    name.split_whitespace().last().unwrap_or("")
+}
+
+/// Party reachable from an IVR menu
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DirectoryEntry {
+    pub extension: String,
+    /// Name read back to the caller
+    pub name: String,
+    /// Transfer destination
+    pub uri: String,
+    /// Recorded name, spelled out when missing
+    pub name_prompt: Option<String>,
+}
+
+impl DirectoryEntry {
+    /// Whether the keypad digits of the surname start with `digits`
+    pub fn matches_keys(&self, digits: &str) -> bool {
+        keypad_digits(surname(&self.name)).starts_with(digits)
+    }
+
+    /// Prompts reading the name back: the recording, else its letters
+    pub fn read_back(&self) -> Vec<String> {
+        match &self.name_prompt {
+            Some(prompt) => vec![prompt.clone()],
+            None => self
+                .name
+                .chars()
+                .filter(char::is_ascii_alphabetic)
+                .map(letter_prompt)
+                .collect(),
+        }
+    }
+}
+
+/// Directory searched by IVR menus
+#[async_trait]
+pub trait IvrDirectory: Send + Sync {
+    /// Party with `extension`, if it is in the dial plan
+    async fn find_extension(&self, realm: Option<&str>, extension: &str) -> Option<DirectoryEntry>;
+
+    /// Parties whose surname is keyed with `digits`, ordered by name
+    async fn find_by_name(&self, realm: Option<&str>, digits: &str) -> Vec<DirectoryEntry>;
+}
+
+/// Directory of the enabled users; the username is the extension
+///
+/// Names come from the display names, and the recorded names from the
+/// users' voicemail boxes.
+pub struct UserIvrDirectory {
+    users: Arc<dyn UserRepository>,
+    voicemail: Option<Arc<dyn VoicemailRepository>>,
+    /// Domain of transfer URIs of users without a realm filter
+    domain: String,
+}
+
+impl UserIvrDirectory {
+    pub fn new(users: Arc<dyn UserRepository>, domain: String) -> Self {
+        Self {
+            users,
+            voicemail: None,
+            domain,
+        }
+    }
+
+    /// Read recorded names from the users' mailboxes
+    pub fn with_voicemail(mut self, voicemail: Arc<dyn VoicemailRepository>) -> Self {
+        self.voicemail = Some(voicemail);
+        self
+    }
+
+    async fn entry(&self, user: &User) -> DirectoryEntry {
+        let name_prompt = match &self.voicemail {
+            Some(voicemail) => match voicemail.get_mailbox(&user.username).await {
+                Ok(mailbox) => mailbox.and_then(|m| m.name_greeting_file),
+                Err(e) => {
+                    warn!("Failed to read recorded name of {}: {}", user.username, e);
+                    None
+                }
+            },
+            None => None,
+        };
+        let domain = if user.realm.is_empty() { &self.domain } else { &user.realm };
+        DirectoryEntry {
+            extension: user.username.clone(),
+            name: DirectoryQuery::entry_name(user).to_string(),
+            uri: format!("sip:{}@{}", user.username, domain),
+            name_prompt,
+        }
+    }
+}
+
+#[async_trait]
+impl IvrDirectory for UserIvrDirectory {
+    async fn find_extension(&self, realm: Option<&str>, extension: &str) -> Option<DirectoryEntry> {
+        let user = match realm {
+            Some(realm) => self.users.find_by_username_and_realm(extension, realm).await,
+            None => self.users.find_by_username(extension).await,
+        };
+        match user {
+            Ok(Some(user)) if user.enabled => Some(self.entry(&user).await),
+            Ok(_) => None,
+            Err(e) => {
+                warn!("Failed to look up extension {}: {}", extension, e);
+                None
+            }
+        }
+    }
+
+    async fn find_by_name(&self, realm: Option<&str>, digits: &str) -> Vec<DirectoryEntry> {
+        let mut users = Vec::new();
+        let mut offset = 0;
+        loop {
+            let query = DirectoryQuery::new(
+                "",
+                realm.map(str::to_string),
+                MAX_DIRECTORY_LIMIT,
+                offset,
+            );
+            let page = match self.users.search(&query).await {
+                Ok(page) => page,
+                Err(e) => {
+                    warn!("Failed to search the directory: {}", e);
+                    break;
+                }
+            };
+            let done = (page.len() as i64) < MAX_DIRECTORY_LIMIT;
+            offset += page.len() as i64;
+            users.extend(page.into_iter().filter(|user| {
+                user.display_name
+                    .as_deref()
+                    .is_some_and(|name| keypad_digits(surname(name)).starts_with(digits))
+            }));
+            if done {
+                break;
+            }
+        }
+
+        let mut entries = Vec::with_capacity(users.len());
+        for user in &users {
+            entries.push(self.entry(user).await);
+        }
+        entries.sort_by(|a, b| a.name.cmp(&b.name));
+        entries
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_keypad_digits() {
+        assert_eq!(keypad_digits("Smith"), "76484");
+        assert_eq!(keypad_digits("O'Brien"), "627436");
+        assert_eq!(keypad_digits(surname("Mary Ann Quinn")), "78466");
+        assert_eq!(keypad_digit('z'), Some('9'));
+        assert_eq!(keypad_digit('1'), None);
+    }
+
+    #[test]
+    fn test_read_back() {
+        let mut entry = DirectoryEntry {
+            extension: "1001".to_string(),
+            name: "Al Li".to_string(),
+            uri: "sip:1001@example.com".to_string(),
+            name_prompt: None,
+        };
+        assert!(entry.matches_keys("54"));
+        assert!(!entry.matches_keys("25"));
+        assert_eq!(
+            entry.read_back(),
+            vec!["ivr/letters/a.wav", "ivr/letters/l.wav", "ivr/letters/l.wav", "ivr/letters/i.wav"]
+        );
+
+        entry.name_prompt = Some("names/1001.wav".to_string());
+        assert_eq!(entry.read_back(), vec!["names/1001.wav"]);
+    }
+}
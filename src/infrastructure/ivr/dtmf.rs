@@ -1,5 +1,11 @@
 /// DTMF (Dual-Tone Multi-Frequency) detection and handling
+use std::collections::HashMap;
+use std::sync::RwLock;
 use std::time::{Duration, Instant};
+use tokio::sync::broadcast;
+
+/// Digits buffered per call for a slow subscriber
+const DTMF_CHANNEL_CAPACITY: usize = 32;
 
 /// DTMF digit representation
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
@@ -115,6 +121,7 @@ impl DtmfEvent {
 }
 
 /// DTMF detector with buffer and timeout
+#[derive(Debug, Clone)]
 pub struct DtmfDetector {
     buffer: Vec<DtmfDigit>,
     max_buffer_size: usize,
@@ -222,7 +229,7 @@ impl DtmfParser {
             let value = parts[1].trim();
 
             match key {
-                "Signal" => signal = value.chars().next(),
+                "Signal" => signal = Self::signal_char(value),
                 "Duration" => {
                     if let Ok(d) = value.parse::<u16>() {
                         duration_ms = d;
@@ -240,6 +247,126 @@ impl DtmfParser {
 
         None
     }
+
+    /// Parse DTMF from SIP INFO application/dtmf body (the digit alone)
+    pub fn parse_dtmf(body: &str) -> Option<DtmfEvent> {
+        let digit = Self::signal_char(body.trim()).and_then(DtmfDigit::from_char)?;
+        Some(DtmfEvent::new(digit, Duration::from_millis(100)))
+    }
+
+    /// Parse a SIP INFO body by content type; None for other content types
+    pub fn parse_info_body(content_type: &str, body: &str) -> Option<Option<DtmfEvent>> {
+        let media_type = content_type.split(';').next().unwrap_or_default().trim();
+        if media_type.eq_ignore_ascii_case("application/dtmf-relay") {
+            Some(Self::parse_sip_info(body))
+        } else if media_type.eq_ignore_ascii_case("application/dtmf") {
+            Some(Self::parse_dtmf(body))
+        } else {
+            None
+        }
+    }
+
+    /// Build an application/dtmf-relay body
+    pub fn to_sip_info(event: &DtmfEvent) -> String {
+        format!(
+            "Signal={}\r\nDuration={}\r\n",
+            event.digit.to_char(),
+            event.duration.as_millis()
+        )
+    }
+
+    /// Signal value as a digit; some gateways send `*` and `#` as 10 and 11
+    fn signal_char(value: &str) -> Option<char> {
+        match value {
+            "10" => Some('*'),
+            "11" => Some('#'),
+            _ => value.chars().next(),
+        }
+    }
+}
+
+/// Per-call stream of received DTMF
+///
+/// RTP telephone-events and SIP INFO digits are published here alike, so
+/// IVR and feature-code consumers do not depend on the transport.
+pub struct DtmfDispatcher {
+    channels: RwLock<HashMap<String, broadcast::Sender<DtmfEvent>>>,
+}
+
+impl DtmfDispatcher {
+    pub fn new() -> Self {
+        Self {
+            channels: RwLock::new(HashMap::new()),
+        }
+    }
+
+    /// Receive the DTMF of a call
+    pub fn subscribe(&self, call_id: &str) -> broadcast::Receiver<DtmfEvent> {
+        self.channels
+            .write()
+            .unwrap()
+            .entry(call_id.to_string())
+            .or_insert_with(|| broadcast::channel(DTMF_CHANNEL_CAPACITY).0)
+            .subscribe()
+    }
+
+    /// Publish a digit of a call; returns the number of subscribers reached
+    pub fn publish(&self, call_id: &str, event: DtmfEvent) -> usize {
+        match self.channels.read().unwrap().get(call_id) {
+            Some(sender) => sender.send(event).unwrap_or(0),
+            None => 0,
+        }
+    }
+
+    /// Publish an RFC 2833 telephone-event of a call
+    pub fn publish_rfc2833(&self, call_id: &str, event_code: u8, duration_ms: u16) -> usize {
+        match DtmfEvent::from_rfc2833(event_code, duration_ms) {
+            Some(event) => self.publish(call_id, event),
+            None => 0,
+        }
+    }
+
+    /// Drop the stream of an ended call
+    pub fn remove(&self, call_id: &str) {
+        self.channels.write().unwrap().remove(call_id);
+    }
+}
+
+impl Default for DtmfDispatcher {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Read digits from a call until `#`, `max_digits` or `timeout`
+///
+/// Returns the digits without the `#`; `None` when the caller entered
+/// nothing in time or the call ended.
+pub async fn collect_digits(
+    digits: &mut broadcast::Receiver<DtmfEvent>,
+    max_digits: usize,
+    timeout: Duration,
+) -> Option<String> {
+    let mut entered = String::new();
+    let collect = async {
+        while entered.len() < max_digits {
+            match digits.recv().await {
+                Ok(event) => match event.digit.to_char() {
+                    '#' => break,
+                    digit => entered.push(digit),
+                },
+                Err(broadcast::error::RecvError::Lagged(_)) => continue,
+                Err(broadcast::error::RecvError::Closed) => return false,
+            }
+        }
+        true
+    };
+    let finished = tokio::time::timeout(timeout, collect).await.unwrap_or(false);
+    if finished && !entered.is_empty() {
+        Some(entered)
+    } else {
+        None
+    }
 }
 
 #[cfg(test)]
@@ -299,6 +426,28 @@ mod tests {
         assert_eq!(event.duration, Duration::from_millis(160));
     }
 
+    #[test]
+    fn test_dtmf_info_bodies() {
+        let event = DtmfParser::parse_info_body("application/dtmf", "#\r\n")
+            .unwrap()
+            .unwrap();
+        assert_eq!(event.digit, DtmfDigit::Pound);
+
+        let event = DtmfParser::parse_info_body("Application/DTMF-Relay", "Signal=10\r\nDuration=250\r\n")
+            .unwrap()
+            .unwrap();
+        assert_eq!(event.digit, DtmfDigit::Star);
+        assert_eq!(event.duration, Duration::from_millis(250));
+
+        assert!(DtmfParser::parse_info_body("application/dtmf-relay", "Signal=x\r\n")
+            .unwrap()
+            .is_none());
+        assert!(DtmfParser::parse_info_body("application/media_control+xml", "<xml/>").is_none());
+
+        let body = DtmfParser::to_sip_info(&DtmfEvent::new(DtmfDigit::Seven, Duration::from_millis(160)));
+        assert_eq!(body, "Signal=7\r\nDuration=160\r\n");
+    }
+
     #[test]
     fn test_dtmf_rfc2833() {
         let event = DtmfEvent::from_rfc2833(5, 160).unwrap();
@@ -315,6 +464,20 @@ mod tests {
         assert!(invalid.is_none());
     }
 
+    #[tokio::test]
+    async fn test_collect_digits_until_pound() {
+        let dispatcher = DtmfDispatcher::new();
+        let mut digits = dispatcher.subscribe("call-1");
+        for digit in ['4', '7', '#', '9'] {
+            let digit = DtmfDigit::from_char(digit).unwrap();
+            dispatcher.publish("call-1", DtmfEvent::new(digit, Duration::from_millis(100)));
+        }
+        let entered = collect_digits(&mut digits, 8, Duration::from_secs(1)).await;
+        assert_eq!(entered.as_deref(), Some("47"));
+        // The 9 alone, never ended with '#'
+        assert_eq!(collect_digits(&mut digits, 8, Duration::from_millis(50)).await, None);
+    }
+
     #[test]
     fn test_get_last_digits() {
         let mut detector = DtmfDetector::default_settings();
@@ -1,8 +1,16 @@
 /// IVR flow engine for executing IVR logic
+use super::directory::{
+    digit_prompt, DirectoryEntry, IvrDirectory, CONFIRM_PROMPT, MORE_LETTERS_PROMPT,
+    NO_MATCH_PROMPT, PRESS_PROMPT,
+};
 use super::dtmf::{DtmfDetector, DtmfEvent};
-use super::menu::{IvrMenu, IvrMenuSystem, MenuAction};
+use super::menu::{
+    DialByName, ExtensionCollection, IvrMenu, IvrMenuSystem, MenuAction, MenuNode,
+};
 use std::collections::HashMap;
 use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::broadcast::{self, error::RecvError};
 use tokio::sync::RwLock;
 use tracing::{debug, info, warn};
 
@@ -15,6 +23,8 @@ pub enum IvrState {
     PlayingGreeting,
     /// Waiting for digit input
     WaitingForInput,
+    /// Collecting the digits of an extension or name
+    CollectingDigits,
     /// Processing input
     ProcessingInput,
     /// Playing audio
@@ -29,7 +39,31 @@ pub enum IvrState {
     Completed,
 }
 
+/// Progress of a dial-by-name search
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub enum NameSearch {
+    /// Letters of the surname are being entered
+    #[default]
+    Spelling,
+    /// Several matches were read back; a key selects one
+    Choosing(Vec<DirectoryEntry>),
+    /// A single match was read back for confirmation
+    Confirming(DirectoryEntry),
+}
+
+/// How a session run by [`IvrFlowEngine::run`] ended
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum IvrOutcome {
+    /// Transfer the caller here
+    Transfer(String),
+    /// Hang up on the caller
+    Hangup,
+    /// The caller's digit stream ended (the call is gone)
+    Ended,
+}
+
 /// IVR flow session
+#[derive(Clone)]
 pub struct IvrSession {
     pub session_id: String,
     pub current_menu_id: Option<String>,
@@ -38,6 +72,7 @@ pub struct IvrSession {
     pub retry_count: u32,
     pub menu_stack: Vec<String>, // For GoBack action
     pub variables: HashMap<String, String>, // Session variables
+    pub name_search: NameSearch,
 }
 
 impl IvrSession {
@@ -50,6 +85,7 @@ impl IvrSession {
             retry_count: 0,
             menu_stack: Vec::new(),
             variables: HashMap::new(),
+            name_search: NameSearch::Spelling,
         }
     }
 
@@ -84,6 +120,8 @@ pub struct IvrFlow {
     pub name: String,
     pub start_menu_id: String,
     pub menu_system: Arc<RwLock<IvrMenuSystem>>,
+    /// Realm (tenant) whose directory extensions and names are looked up in
+    pub realm: Option<String>,
 }
 
 impl IvrFlow {
@@ -93,9 +131,15 @@ impl IvrFlow {
             name,
             start_menu_id,
             menu_system: Arc::new(RwLock::new(menu_system)),
+            realm: None,
         }
     }
 
+    pub fn with_realm(mut self, realm: String) -> Self {
+        self.realm = Some(realm);
+        self
+    }
+
     /// Get menu system
     pub async fn get_menu_system(&self) -> tokio::sync::RwLockReadGuard<'_, IvrMenuSystem> {
         self.menu_system.read().await
@@ -105,15 +149,25 @@ impl IvrFlow {
 /// IVR flow engine
 pub struct IvrFlowEngine {
     sessions: Arc<RwLock<HashMap<String, IvrSession>>>,
+    /// Extensions and names of collection and dial-by-name menus
+    directory: Option<Arc<dyn IvrDirectory>>,
 }
 
 impl IvrFlowEngine {
     pub fn new() -> Self {
         Self {
             sessions: Arc::new(RwLock::new(HashMap::new())),
+            directory: None,
         }
     }
 
+    /// Directory of extension-collection and dial-by-name menus; without
+    /// it no extension or name is found
+    pub fn with_directory(mut self, directory: Arc<dyn IvrDirectory>) -> Self {
+        self.directory = Some(directory);
+        self
+    }
+
     /// Start a new IVR session
     pub async fn start_session(&self, session_id: String, flow: &IvrFlow) -> Result<IvrSession, String> {
         let mut session = IvrSession::new(session_id.clone());
@@ -154,6 +208,16 @@ impl IvrFlowEngine {
         let menu = menu_system.get_menu(&current_menu_id)
             .ok_or_else(|| format!("Menu {} not found", current_menu_id))?;
 
+        match &menu.node {
+            MenuNode::Options => {}
+            MenuNode::CollectExtension(collection) => {
+                return Ok(self.collect_extension(session, menu, collection, digit, flow).await);
+            }
+            MenuNode::DialByName(dial_by_name) => {
+                return Ok(self.dial_by_name(session, menu, dial_by_name, digit, flow).await);
+            }
+        }
+
         // Check if digit is valid
         if !menu.is_valid_digit(digit) {
             session.retry_count += 1;
@@ -175,7 +239,13 @@ impl IvrFlowEngine {
         session.retry_count = 0; // Reset retry count on valid input
         session.dtmf_detector.clear_buffer();
 
-        // Process action
+        Ok(Self::apply_action(session, action))
+    }
+
+    /// Carry out the action of a selected menu item
+    fn apply_action(session: &mut IvrSession, action: MenuAction) -> MenuAction {
+        let session_id = session.session_id.clone();
+        session.name_search = NameSearch::Spelling;
         match &action {
             MenuAction::GotoMenu(menu_id) => {
                 info!("Going to menu {} from session {}", menu_id, session_id);
@@ -190,7 +260,7 @@ impl IvrFlowEngine {
                 } else {
                     warn!("No previous menu to go back to");
                     session.state = IvrState::Completed;
-                    return Ok(MenuAction::Hangup);
+                    return MenuAction::Hangup;
                 }
             }
             MenuAction::Repeat => {
@@ -214,9 +284,351 @@ impl IvrFlowEngine {
             }
         }
 
+        action
+    }
+
+    /// Count a wrong or missing input; replays `prompts`, or hangs up after
+    /// the menu's retries
+    fn retry(session: &mut IvrSession, menu: &IvrMenu, prompts: Vec<String>) -> MenuAction {
+        session.retry_count += 1;
+        session.dtmf_detector.clear_buffer();
+        if session.retry_count >= menu.max_retries {
+            warn!("Max retries exceeded for session {}", session.session_id);
+            session.state = IvrState::Completed;
+            return MenuAction::Hangup;
+        }
+        session.state = IvrState::InvalidInput;
+        MenuAction::PlayPrompts(prompts)
+    }
+
+    fn transfer_to(session: &mut IvrSession, entry: &DirectoryEntry) -> MenuAction {
+        info!(
+            "Transferring session {} to {} ({})",
+            session.session_id, entry.name, entry.uri
+        );
+        session.retry_count = 0;
+        session.dtmf_detector.clear_buffer();
+        session.name_search = NameSearch::Spelling;
+        session.state = IvrState::Transferring(entry.uri.clone());
+        MenuAction::Transfer(entry.uri.clone())
+    }
+
+    /// Digit entered in an extension-collection menu
+    async fn collect_extension(
+        &self,
+        session: &mut IvrSession,
+        menu: &IvrMenu,
+        collection: &ExtensionCollection,
+        digit: char,
+        flow: &IvrFlow,
+    ) -> MenuAction {
+        let entered = session.dtmf_detector.get_buffer();
+        // Extensions are digits: '#' ends one, a leading '*' is a menu item
+        if digit == '#' || entered == "*" || entered.len() >= collection.max_digits {
+            return self.finish_extension(session, menu, collection, flow).await;
+        }
+        session.state = IvrState::CollectingDigits;
+        MenuAction::Collecting
+    }
+
+    /// Run the menu item or transfer to the extension entered
+    async fn finish_extension(
+        &self,
+        session: &mut IvrSession,
+        menu: &IvrMenu,
+        collection: &ExtensionCollection,
+        flow: &IvrFlow,
+    ) -> MenuAction {
+        let entered: String = session
+            .dtmf_detector
+            .get_buffer()
+            .chars()
+            .filter(|c| *c != '#')
+            .collect();
+        session.dtmf_detector.clear_buffer();
+
+        let mut chars = entered.chars();
+        if let (Some(digit), None) = (chars.next(), chars.next()) {
+            if let Some(item) = menu.get_item(digit) {
+                session.retry_count = 0;
+                return Self::apply_action(session, item.action.clone());
+            }
+        }
+
+        let entry = match &self.directory {
+            Some(directory) if !entered.is_empty() && entered.chars().all(|c| c.is_ascii_digit()) => {
+                directory.find_extension(flow.realm.as_deref(), &entered).await
+            }
+            _ => None,
+        };
+        match entry {
+            Some(entry) => Self::transfer_to(session, &entry),
+            None => {
+                debug!("Session {}: {} is not an extension", session.session_id, entered);
+                Self::retry(
+                    session,
+                    menu,
+                    vec![collection.invalid_prompt.clone(), menu.greeting_file.clone()],
+                )
+            }
+        }
+    }
+
+    /// Digit entered in a dial-by-name menu; `#` ends the spelling
+    async fn dial_by_name(
+        &self,
+        session: &mut IvrSession,
+        menu: &IvrMenu,
+        config: &DialByName,
+        digit: char,
+        flow: &IvrFlow,
+    ) -> MenuAction {
+        if digit == '*' {
+            session.dtmf_detector.clear_buffer();
+            session.name_search = NameSearch::Spelling;
+            session.state = IvrState::PlayingGreeting;
+            return MenuAction::PlayPrompts(vec![menu.greeting_file.clone()]);
+        }
+
+        match session.name_search.clone() {
+            NameSearch::Choosing(matches) => {
+                session.dtmf_detector.clear_buffer();
+                match digit
+                    .to_digit(10)
+                    .filter(|key| (1..=matches.len() as u32).contains(key))
+                {
+                    Some(key) => Self::transfer_to(session, &matches[key as usize - 1]),
+                    None => Self::retry(session, menu, Self::choice_prompts(&matches)),
+                }
+            }
+            NameSearch::Confirming(entry) => {
+                session.dtmf_detector.clear_buffer();
+                if digit == '1' {
+                    Self::transfer_to(session, &entry)
+                } else {
+                    Self::retry(session, menu, Self::confirm_prompts(&entry))
+                }
+            }
+            NameSearch::Spelling => {
+                let finished = digit == '#';
+                // Only 2-9 carry letters
+                let keys: String = session
+                    .dtmf_detector
+                    .get_buffer()
+                    .chars()
+                    .filter(|c| ('2'..='9').contains(c))
+                    .collect();
+                if keys.len() < config.min_letters {
+                    if finished {
+                        return Self::retry(session, menu, vec![menu.greeting_file.clone()]);
+                    }
+                    session.state = IvrState::CollectingDigits;
+                    return MenuAction::Collecting;
+                }
+
+                let mut matches = match &self.directory {
+                    Some(directory) => directory.find_by_name(flow.realm.as_deref(), &keys).await,
+                    None => Vec::new(),
+                };
+                debug!(
+                    "Session {}: {} names match {}",
+                    session.session_id,
+                    matches.len(),
+                    keys
+                );
+                if matches.is_empty() {
+                    return Self::retry(
+                        session,
+                        menu,
+                        vec![NO_MATCH_PROMPT.to_string(), menu.greeting_file.clone()],
+                    );
+                }
+                if matches.len() > config.max_choices && !finished {
+                    session.state = IvrState::CollectingDigits;
+                    return if keys.len() == config.min_letters {
+                        MenuAction::PlayPrompts(vec![MORE_LETTERS_PROMPT.to_string()])
+                    } else {
+                        MenuAction::Collecting
+                    };
+                }
+
+                session.dtmf_detector.clear_buffer();
+                session.state = IvrState::WaitingForInput;
+                matches.truncate(config.max_choices.clamp(1, 9));
+                if matches.len() == 1 {
+                    let entry = matches.remove(0);
+                    let prompts = Self::confirm_prompts(&entry);
+                    session.name_search = NameSearch::Confirming(entry);
+                    MenuAction::PlayPrompts(prompts)
+                } else {
+                    let prompts = Self::choice_prompts(&matches);
+                    session.name_search = NameSearch::Choosing(matches);
+                    MenuAction::PlayPrompts(prompts)
+                }
+            }
+        }
+    }
+
+    /// Each match read back, followed by the key selecting it
+    fn choice_prompts(matches: &[DirectoryEntry]) -> Vec<String> {
+        matches
+            .iter()
+            .enumerate()
+            .flat_map(|(index, entry)| {
+                let key = char::from_digit(index as u32 + 1, 10).unwrap_or('9');
+                entry
+                    .read_back()
+                    .into_iter()
+                    .chain([PRESS_PROMPT.to_string(), digit_prompt(key)])
+            })
+            .collect()
+    }
+
+    fn confirm_prompts(entry: &DirectoryEntry) -> Vec<String> {
+        let mut prompts = entry.read_back();
+        prompts.push(CONFIRM_PROMPT.to_string());
+        prompts
+    }
+
+    /// How long to wait for the next digit of a session
+    ///
+    /// The inter-digit timeout while an extension or name is being entered,
+    /// else the menu's timeout.
+    pub async fn input_timeout(&self, session_id: &str, flow: &IvrFlow) -> Duration {
+        let sessions = self.sessions.read().await;
+        let menu_system = flow.menu_system.read().await;
+        let Some((session, menu)) = sessions.get(session_id).and_then(|session| {
+            let menu = menu_system.get_menu(session.current_menu_id.as_deref()?)?;
+            Some((session, menu))
+        }) else {
+            return Duration::from_secs(5);
+        };
+        let collecting = session.dtmf_detector.buffer_length() > 0;
+        let secs = match &menu.node {
+            MenuNode::CollectExtension(c) if collecting => c.inter_digit_timeout_secs,
+            MenuNode::DialByName(d) if collecting => d.inter_digit_timeout_secs,
+            _ => menu.timeout_seconds,
+        };
+        Duration::from_secs(secs.max(1) as u64)
+    }
+
+    /// No digit came within [`Self::input_timeout`]
+    ///
+    /// Ends an extension or name being entered; otherwise counts a retry
+    /// and replays what the caller is asked for.
+    pub async fn handle_input_timeout(
+        &self,
+        session_id: &str,
+        flow: &IvrFlow,
+    ) -> Result<MenuAction, String> {
+        let mut sessions = self.sessions.write().await;
+        let session = sessions
+            .get_mut(session_id)
+            .ok_or_else(|| "Session not found".to_string())?;
+        let current_menu_id = session.current_menu_id.clone()
+            .ok_or_else(|| "No current menu".to_string())?;
+        let menu_system = flow.menu_system.read().await;
+        let menu = menu_system.get_menu(&current_menu_id)
+            .ok_or_else(|| format!("Menu {} not found", current_menu_id))?;
+
+        if session.dtmf_detector.buffer_length() > 0 {
+            match &menu.node {
+                MenuNode::CollectExtension(collection) => {
+                    return Ok(self.finish_extension(session, menu, collection, flow).await);
+                }
+                MenuNode::DialByName(dial_by_name) => {
+                    return Ok(self.dial_by_name(session, menu, dial_by_name, '#', flow).await);
+                }
+                MenuNode::Options => {}
+            }
+        }
+
+        warn!("Timeout for session {} (retry {})", session_id, session.retry_count + 1);
+        let mut prompts: Vec<String> = menu.timeout_sound.iter().cloned().collect();
+        prompts.extend(match &session.name_search {
+            NameSearch::Choosing(matches) => Self::choice_prompts(matches),
+            NameSearch::Confirming(entry) => Self::confirm_prompts(entry),
+            NameSearch::Spelling => vec![menu.greeting_file.clone()],
+        });
+        let action = Self::retry(session, menu, prompts);
+        if action != MenuAction::Hangup {
+            session.state = IvrState::Timeout;
+        }
         Ok(action)
     }
 
+    /// Drive a session with a call's digits until it transfers or hangs up
+    ///
+    /// The greeting of the start menu is assumed to be playing; `play` is
+    /// called with the prompts of every later step.
+    pub async fn run<F>(
+        &self,
+        session_id: &str,
+        flow: &IvrFlow,
+        digits: &mut broadcast::Receiver<DtmfEvent>,
+        play: F,
+    ) -> IvrOutcome
+    where
+        F: Fn(&[String]),
+    {
+        if let Err(e) = self.start_session(session_id.to_string(), flow).await {
+            warn!("Failed to start IVR session {}: {}", session_id, e);
+            return IvrOutcome::Hangup;
+        }
+
+        let outcome = loop {
+            let wait = self.input_timeout(session_id, flow).await;
+            let action = match tokio::time::timeout(wait, digits.recv()).await {
+                Ok(Ok(event)) => self.process_dtmf(session_id, event, flow).await,
+                Ok(Err(RecvError::Lagged(missed))) => {
+                    debug!("IVR session {} missed {} digits", session_id, missed);
+                    continue;
+                }
+                Ok(Err(RecvError::Closed)) => break IvrOutcome::Ended,
+                Err(_) => self.handle_input_timeout(session_id, flow).await,
+            };
+
+            match action {
+                Ok(MenuAction::Transfer(destination)) => break IvrOutcome::Transfer(destination),
+                Ok(MenuAction::Hangup) => break IvrOutcome::Hangup,
+                Ok(MenuAction::PlayAudio(file)) => play(&[file]),
+                Ok(MenuAction::PlayPrompts(prompts)) => play(&prompts),
+                Ok(MenuAction::GotoMenu(_)) | Ok(MenuAction::GoBack) | Ok(MenuAction::Repeat) => {
+                    play(&self.current_prompts(session_id, flow, |menu| {
+                        Some(menu.greeting_file.clone())
+                    }).await)
+                }
+                Ok(_) => {}
+                Err(e) => {
+                    debug!("IVR session {}: {}", session_id, e);
+                    play(&self.current_prompts(session_id, flow, |menu| {
+                        menu.invalid_sound.clone()
+                    }).await)
+                }
+            }
+        };
+
+        self.end_session(session_id).await;
+        outcome
+    }
+
+    /// Prompt `select` picks from the session's current menu
+    async fn current_prompts(
+        &self,
+        session_id: &str,
+        flow: &IvrFlow,
+        select: impl Fn(&IvrMenu) -> Option<String>,
+    ) -> Vec<String> {
+        let sessions = self.sessions.read().await;
+        let menu_system = flow.menu_system.read().await;
+        sessions
+            .get(session_id)
+            .and_then(|session| menu_system.get_menu(session.current_menu_id.as_deref()?))
+            .and_then(select)
+            .into_iter()
+            .collect()
+    }
+
     /// Handle timeout for a session
     pub async fn handle_timeout(&self, session_id: &str) -> Result<(), String> {
         let mut sessions = self.sessions.write().await;
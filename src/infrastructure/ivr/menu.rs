@@ -4,7 +4,7 @@ use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
 
 /// Action to take when menu item is selected
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub enum MenuAction {
     /// Play audio file
     PlayAudio(String),
@@ -24,6 +24,72 @@ pub enum MenuAction {
     Hangup,
     /// Custom action
     Custom(String),
+    /// Play prompts in order, then wait for input
+    PlayPrompts(Vec<String>),
+    /// Digits are being collected; wait for more
+    Collecting,
+}
+
+/// How a menu treats the caller's digits
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum MenuNode {
+    /// Each digit selects an item
+    #[default]
+    Options,
+    /// Digits are collected into an extension and transferred to
+    CollectExtension(ExtensionCollection),
+    /// Digits spell a surname that is looked up in the directory
+    DialByName(DialByName),
+}
+
+/// Extension dialing from a menu
+///
+/// Collection ends with `#`, after `max_digits` digits or when no digit
+/// came for `inter_digit_timeout_secs`. A single digit that selects a menu
+/// item runs the item; anything else must be an extension of the directory.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ExtensionCollection {
+    pub max_digits: usize,
+    pub inter_digit_timeout_secs: u32,
+    /// Played when the digits are not an extension
+    pub invalid_prompt: String,
+}
+
+impl Default for ExtensionCollection {
+    fn default() -> Self {
+        Self {
+            max_digits: 6,
+            inter_digit_timeout_secs: 3,
+            invalid_prompt: "ivr/invalid_extension.wav".to_string(),
+        }
+    }
+}
+
+/// Dial-by-name directory
+///
+/// The caller spells the surname on the keypad; once `min_letters` keys
+/// were pressed the directory is searched. A single match is read back for
+/// confirmation, several are read back with the key selecting each.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct DialByName {
+    pub min_letters: usize,
+    /// Most matches read back; with more the caller is asked for another
+    /// letter (at most 9)
+    pub max_choices: usize,
+    pub inter_digit_timeout_secs: u32,
+}
+
+impl Default for DialByName {
+    fn default() -> Self {
+        Self {
+            min_letters: 3,
+            max_choices: 8,
+            inter_digit_timeout_secs: 5,
+        }
+    }
 }
 
 /// IVR menu item
@@ -55,6 +121,8 @@ pub struct IvrMenu {
     pub max_retries: u32,
     pub invalid_sound: Option<String>,
     pub timeout_sound: Option<String>,
+    #[serde(default)]
+    pub node: MenuNode,
 }
 
 impl IvrMenu {
@@ -68,6 +136,7 @@ impl IvrMenu {
             max_retries: 3,
             invalid_sound: Some("ivr/invalid.wav".to_string()),
             timeout_sound: Some("ivr/timeout.wav".to_string()),
+            node: MenuNode::Options,
         }
     }
 
@@ -114,6 +183,11 @@ impl IvrMenuBuilder {
         self
     }
 
+    pub fn node(mut self, node: MenuNode) -> Self {
+        self.menu.node = node;
+        self
+    }
+
     pub fn add_item(mut self, digit: char, label: String, action: MenuAction) -> Self {
         self.menu.add_item(IvrMenuItem::new(digit, label, action));
         self
@@ -125,6 +199,7 @@ impl IvrMenuBuilder {
 }
 
 /// IVR menu system manager
+#[derive(Debug, Clone)]
 pub struct IvrMenuSystem {
     menus: HashMap<String, IvrMenu>,
 }
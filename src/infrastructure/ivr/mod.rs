@@ -1,8 +1,13 @@
 /// Interactive Voice Response (IVR) system
+pub mod directory;
 pub mod dtmf;
 pub mod flow;
 pub mod menu;
 
-pub use dtmf::{DtmfDetector, DtmfDigit};
-pub use flow::{IvrFlow, IvrFlowEngine};
-pub use menu::{IvrMenu, IvrMenuItem, MenuAction};
+pub use directory::{DirectoryEntry, IvrDirectory, UserIvrDirectory};
+pub use dtmf::{collect_digits, DtmfDetector, DtmfDigit, DtmfDispatcher, DtmfEvent, DtmfParser};
+pub use flow::{IvrFlow, IvrFlowEngine, IvrOutcome, NameSearch};
+pub use menu::{
+    DialByName, ExtensionCollection, IvrMenu, IvrMenuBuilder, IvrMenuItem, IvrMenuSystem,
+    MenuAction, MenuNode,
+};
@@ -0,0 +1,312 @@
+//! REST number portability lookup
+//!
+//! `GET`s the configured URL for the E.164 number and reads
+//! `routing_number` and `trunk` from the JSON answer; 404 means the number
+//! is not ported. After `failure_threshold` consecutive failures (errors,
+//! timeouts, 5xx) the service is left alone for `open_secs`, then a single
+//! request decides whether it is asked again.
+//!
+//! Plain HTTP only, like the transcription client: the service is expected
+//! on the local network or behind a TLS-terminating proxy.
+
+use super::LnpConfig;
+use crate::domain::shared::SecretBox;
+use crate::domain::routing::{RoutingLookup, RoutingOverride};
+use crate::infrastructure::clock::{system_clock, Clock};
+use async_trait::async_trait;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+use tracing::{info, warn};
+
+/// Larger answers are refused (an answer is a few dozen bytes)
+const MAX_RESPONSE_BYTES: usize = 64 * 1024;
+
+#[derive(Debug, Default)]
+struct Breaker {
+    failures: u32,
+    open_until: Option<tokio::time::Instant>,
+}
+
+/// Asks a REST service where ported numbers are routed
+pub struct HttpRoutingLookup {
+    /// `host:port` connected to and sent as Host
+    authority: String,
+    /// Path with a `{number}` placeholder
+    path: String,
+    api_key: Option<SecretBox>,
+    timeout: Duration,
+    failure_threshold: u32,
+    open_for: Duration,
+    breaker: Mutex<Breaker>,
+    clock: Arc<dyn Clock>,
+}
+
+impl HttpRoutingLookup {
+    pub fn new(config: &LnpConfig) -> Result<Self, String> {
+        let endpoint = config
+            .endpoint
+            .as_deref()
+            .ok_or("Number portability lookups need an endpoint")?;
+        let rest = endpoint.strip_prefix("http://").ok_or_else(|| {
+            format!(
+                "Unsupported LNP endpoint {} (only http:// is supported)",
+                endpoint
+            )
+        })?;
+        let (authority, path) = match rest.find('/') {
+            Some(i) => (&rest[..i], &rest[i..]),
+            None => (rest, "/"),
+        };
+        if authority.is_empty() {
+            return Err(format!("LNP endpoint {} has no host", endpoint));
+        }
+        let has_port = match authority.rfind(']') {
+            Some(i) => authority[i..].contains(':'),
+            None => authority.contains(':'),
+        };
+        let authority = if has_port {
+            authority.to_string()
+        } else {
+            format!("{}:80", authority)
+        };
+        let path = if path.contains("{number}") {
+            path.to_string()
+        } else {
+            format!("{}/{{number}}", path.trim_end_matches('/'))
+        };
+
+        Ok(Self {
+            authority,
+            path,
+            api_key: config.api_key.clone(),
+            timeout: Duration::from_millis(config.timeout_ms),
+            failure_threshold: config.failure_threshold.max(1),
+            open_for: Duration::from_secs(config.open_secs),
+            breaker: Mutex::new(Breaker::default()),
+            clock: system_clock(),
+        })
+    }
+
+    /// Time the circuit breaker by `clock`
+    pub fn with_clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    async fn get(&self, number: &str) -> Result<(u16, Vec<u8>), String> {
+        // `+` is a space in some query parsers
+        let path = self.path.replace("{number}", &number.replace('+', "%2B"));
+        // HTTP/1.0, so the answer is never chunked
+        let mut head = format!(
+            "GET {} HTTP/1.0\r\n\
+             Host: {}\r\n\
+             User-Agent: yakyak\r\n\
+             Accept: application/json\r\n",
+            path, self.authority
+        );
+        if let Some(api_key) = &self.api_key {
+            head.push_str(&format!("Authorization: Bearer {}\r\n", api_key.expose()));
+        }
+        head.push_str("\r\n");
+
+        let mut stream = TcpStream::connect(&self.authority)
+            .await
+            .map_err(|e| format!("Failed to connect to {}: {}", self.authority, e))?;
+        stream
+            .write_all(head.as_bytes())
+            .await
+            .map_err(|e| format!("Failed to send lookup: {}", e))?;
+        let mut response = Vec::new();
+        (&mut stream)
+            .take(MAX_RESPONSE_BYTES as u64 + 1)
+            .read_to_end(&mut response)
+            .await
+            .map_err(|e| format!("Failed to read lookup answer: {}", e))?;
+        if response.len() > MAX_RESPONSE_BYTES {
+            return Err(format!("LNP answer exceeds {} bytes", MAX_RESPONSE_BYTES));
+        }
+        split_response(&response)
+    }
+
+    /// Whether the service may be asked now
+    fn allow(&self) -> bool {
+        let breaker = self.breaker.lock().unwrap();
+        breaker
+            .open_until
+            .is_none_or(|until| self.clock.instant() >= until)
+    }
+
+    fn record(&self, success: bool) {
+        let mut breaker = self.breaker.lock().unwrap();
+        if success {
+            if breaker.open_until.take().is_some() {
+                info!("LNP service {} answering again", self.authority);
+            }
+            breaker.failures = 0;
+            return;
+        }
+        breaker.failures += 1;
+        if breaker.failures >= self.failure_threshold {
+            warn!(
+                "LNP service {} failed {} times; not asking it for {:?}",
+                self.authority, breaker.failures, self.open_for
+            );
+            breaker.open_until = Some(self.clock.instant() + self.open_for);
+        }
+    }
+}
+
+#[async_trait]
+impl RoutingLookup for HttpRoutingLookup {
+    async fn lookup(&self, number: &str) -> Result<RoutingOverride, String> {
+        if !self.allow() {
+            return Err(format!("LNP service {} unavailable", self.authority));
+        }
+        let answer = match tokio::time::timeout(self.timeout, self.get(number)).await {
+            Ok(answer) => answer,
+            Err(_) => Err(format!("LNP lookup timed out after {:?}", self.timeout)),
+        };
+        // A number the service refuses (4xx) is not the service failing
+        let (result, failed) = match answer {
+            Ok((404, _)) => (Ok(RoutingOverride::none()), false),
+            Ok((200..=299, body)) => {
+                let result = parse_override(&body);
+                let failed = result.is_err();
+                (result, failed)
+            }
+            Ok((status, body)) => {
+                let detail: String = String::from_utf8_lossy(&body).chars().take(200).collect();
+                (
+                    Err(format!("LNP service answered {}: {}", status, detail.trim())),
+                    status >= 500,
+                )
+            }
+            Err(e) => (Err(e), true),
+        };
+        self.record(!failed);
+        result
+    }
+}
+
+/// Status and body of an HTTP response
+fn split_response(response: &[u8]) -> Result<(u16, Vec<u8>), String> {
+    let split = response
+        .windows(4)
+        .position(|w| w == b"\r\n\r\n")
+        .ok_or("Malformed LNP answer")?;
+    let head = String::from_utf8_lossy(&response[..split]);
+    let status = head
+        .lines()
+        .next()
+        .and_then(|line| line.split_whitespace().nth(1))
+        .and_then(|code| code.parse().ok())
+        .ok_or("Malformed LNP answer status")?;
+    Ok((status, response[split + 4..].to_vec()))
+}
+
+fn parse_override(body: &[u8]) -> Result<RoutingOverride, String> {
+    let json: serde_json::Value =
+        serde_json::from_slice(body).map_err(|e| format!("Invalid LNP answer: {}", e))?;
+    let field = |name: &str| {
+        json.get(name)
+            .and_then(|value| value.as_str())
+            .map(str::trim)
+            .filter(|value| !value.is_empty())
+            .map(str::to_string)
+    };
+    Ok(RoutingOverride {
+        routing_number: field("routing_number"),
+        trunk: field("trunk"),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::ManualClock;
+    use tokio::net::TcpListener;
+
+    /// Serve `answers` in order, one connection each; returns the request
+    /// lines received
+    async fn serve(
+        answers: Vec<(Duration, &'static str)>,
+    ) -> (String, tokio::task::JoinHandle<Vec<String>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let endpoint = format!("http://{}/v1/lnp/{{number}}", listener.local_addr().unwrap());
+        let server = tokio::spawn(async move {
+            let mut requests = Vec::new();
+            for (delay, answer) in answers {
+                let (mut stream, _) = listener.accept().await.unwrap();
+                let mut buf = vec![0u8; 4096];
+                let n = stream.read(&mut buf).await.unwrap();
+                let head = String::from_utf8_lossy(&buf[..n]).to_string();
+                requests.push(head.lines().next().unwrap_or_default().to_string());
+                tokio::time::sleep(delay).await;
+                let _ = stream.write_all(answer.as_bytes()).await;
+            }
+            requests
+        });
+        (endpoint, server)
+    }
+
+    fn lookup(endpoint: String) -> HttpRoutingLookup {
+        HttpRoutingLookup::new(&LnpConfig {
+            enabled: true,
+            endpoint: Some(endpoint),
+            timeout_ms: 200,
+            failure_threshold: 2,
+            open_secs: 30,
+            ..Default::default()
+        })
+        .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_ported_and_not_ported() {
+        let (endpoint, server) = serve(vec![
+            (
+                Duration::ZERO,
+                "HTTP/1.0 200 OK\r\nContent-Type: application/json\r\n\r\n\
+                 {\"routing_number\":\"+14155550000\",\"trunk\":\"carrier-b\"}",
+            ),
+            (Duration::ZERO, "HTTP/1.0 404 Not Found\r\n\r\n"),
+        ])
+        .await;
+        let lookup = lookup(endpoint);
+
+        let routing = lookup.lookup("+14155551234").await.unwrap();
+        assert_eq!(routing.routing_number.as_deref(), Some("+14155550000"));
+        assert_eq!(routing.trunk.as_deref(), Some("carrier-b"));
+        assert!(lookup.lookup("+14155559999").await.unwrap().is_none());
+
+        let requests = server.await.unwrap();
+        assert_eq!(requests[0], "GET /v1/lnp/%2B14155551234 HTTP/1.0");
+    }
+
+    #[tokio::test]
+    async fn test_circuit_opens_after_failures() {
+        let (endpoint, server) = serve(vec![
+            (Duration::from_millis(300), "HTTP/1.0 200 OK\r\n\r\n{}"),
+            (Duration::ZERO, "HTTP/1.0 503 Service Unavailable\r\n\r\n"),
+            (Duration::ZERO, "HTTP/1.0 200 OK\r\n\r\n{}"),
+        ])
+        .await;
+        let clock = Arc::new(ManualClock::new());
+        let lookup = lookup(endpoint).with_clock(clock.clone());
+
+        let timeout = lookup.lookup("+14155551234").await.unwrap_err();
+        assert!(timeout.contains("timed out"), "{}", timeout);
+        assert!(lookup.lookup("+14155551234").await.is_err());
+
+        // Open: not even a connection
+        let open = lookup.lookup("+14155551234").await.unwrap_err();
+        assert!(open.contains("unavailable"), "{}", open);
+
+        // After open_secs one trial closes it again
+        clock.advance(Duration::from_secs(31));
+        assert!(lookup.lookup("+14155551234").await.unwrap().is_none());
+        assert_eq!(server.await.unwrap().len(), 3);
+    }
+}
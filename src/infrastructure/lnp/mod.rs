@@ -0,0 +1,441 @@
+//! Number portability lookups for outbound routing
+//!
+//! [`LnpResolver`] normalizes the dialed number to E.164, answers from a
+//! TTL cache when it can and otherwise asks the configured
+//! [`RoutingLookup`] (the REST service of [`HttpRoutingLookup`], or nothing
+//! without one). A call waits at most `call_budget_ms` for the answer: a
+//! slower lookup goes on in the background to fill the cache while the
+//! call is routed as dialed.
+
+mod http;
+
+pub use http::HttpRoutingLookup;
+
+use crate::domain::routing::{
+    Explain, Explanation, RoutedCall, RoutingLookup, RoutingOverride,
+};
+use crate::domain::shared::{NumberingPlan, SecretBox};
+use crate::domain::tenant_branding::realm_of;
+use crate::infrastructure::clock::{system_clock, Clock};
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use metrics::{counter, gauge, histogram};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tracing::{debug, warn};
+
+/// Number portability settings
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct LnpConfig {
+    pub enabled: bool,
+    /// REST lookup URL; `{number}` is replaced by the E.164 number, which
+    /// is appended when the URL has no placeholder
+    /// (`http://lnp.example.net:8080/v1/lookup/{number}`)
+    pub endpoint: Option<String>,
+    /// Sent as Bearer token when set
+    pub api_key: Option<SecretBox>,
+    /// Milliseconds a request to the service may take
+    pub timeout_ms: u64,
+    /// Milliseconds a call waits for a lookup before it is routed as dialed
+    pub call_budget_ms: u64,
+    /// Seconds answers (ported or not) are cached
+    pub cache_ttl_secs: u64,
+    pub cache_capacity: usize,
+    /// Consecutive failures after which the service is not asked
+    pub failure_threshold: u32,
+    /// Seconds the service is not asked after `failure_threshold` failures
+    pub open_secs: u64,
+}
+
+impl Default for LnpConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            endpoint: None,
+            api_key: None,
+            timeout_ms: 1000,
+            call_budget_ms: 300,
+            cache_ttl_secs: 3600,
+            cache_capacity: 100_000,
+            failure_threshold: 5,
+            open_secs: 30,
+        }
+    }
+}
+
+impl LnpConfig {
+    pub fn validate(&self) -> Result<(), String> {
+        if self.enabled && self.endpoint.is_none() {
+            return Err("enabled number portability lookups need an endpoint".to_string());
+        }
+        if self.timeout_ms == 0 || self.call_budget_ms == 0 {
+            return Err("timeout_ms and call_budget_ms must be at least 1".to_string());
+        }
+        if self.cache_ttl_secs == 0 || self.cache_capacity == 0 {
+            return Err("cache_ttl_secs and cache_capacity must be at least 1".to_string());
+        }
+        if self.failure_threshold == 0 {
+            return Err("failure_threshold must be at least 1".to_string());
+        }
+        Ok(())
+    }
+}
+
+/// A cached lookup answer
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CachedRoute {
+    pub number: String,
+    #[serde(rename = "override")]
+    pub routing: RoutingOverride,
+    pub fetched_at: DateTime<Utc>,
+    pub expires_in_secs: u64,
+}
+
+/// Cache size and hit counts
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct CacheStats {
+    pub entries: usize,
+    pub hits: u64,
+    pub misses: u64,
+}
+
+#[derive(Debug, Clone)]
+struct CacheEntry {
+    routing: RoutingOverride,
+    fetched_at: DateTime<Utc>,
+    expires_at: tokio::time::Instant,
+}
+
+/// Lookup answers by E.164 number, each kept for the TTL
+pub struct LnpCache {
+    ttl: Duration,
+    capacity: usize,
+    clock: Arc<dyn Clock>,
+    entries: Mutex<HashMap<String, CacheEntry>>,
+    hits: AtomicU64,
+    misses: AtomicU64,
+}
+
+impl LnpCache {
+    pub fn new(ttl: Duration, capacity: usize, clock: Arc<dyn Clock>) -> Self {
+        Self {
+            ttl,
+            capacity: capacity.max(1),
+            clock,
+            entries: Mutex::new(HashMap::new()),
+            hits: AtomicU64::new(0),
+            misses: AtomicU64::new(0),
+        }
+    }
+
+    /// Unexpired answer for `number`, counted as hit or miss
+    pub fn get(&self, number: &str) -> Option<RoutingOverride> {
+        let routing = self.entry(number).map(|entry| entry.routing);
+        let result = if routing.is_some() {
+            self.hits.fetch_add(1, Ordering::Relaxed);
+            "hit"
+        } else {
+            self.misses.fetch_add(1, Ordering::Relaxed);
+            "miss"
+        };
+        counter!("lnp_cache_lookups_total", "result" => result).increment(1);
+        routing
+    }
+
+    /// Unexpired entry for `number`, without counting
+    pub fn entry(&self, number: &str) -> Option<CachedRoute> {
+        let now = self.clock.instant();
+        let mut entries = self.entries.lock().unwrap();
+        match entries.get(number) {
+            Some(entry) if entry.expires_at > now => Some(CachedRoute {
+                number: number.to_string(),
+                routing: entry.routing.clone(),
+                fetched_at: entry.fetched_at,
+                expires_in_secs: (entry.expires_at - now).as_secs(),
+            }),
+            Some(_) => {
+                entries.remove(number);
+                gauge!("lnp_cache_entries").set(entries.len() as f64);
+                None
+            }
+            None => None,
+        }
+    }
+
+    pub fn insert(&self, number: &str, routing: RoutingOverride) {
+        let now = self.clock.instant();
+        let mut entries = self.entries.lock().unwrap();
+        if entries.len() >= self.capacity && !entries.contains_key(number) {
+            entries.retain(|_, entry| entry.expires_at > now);
+            if entries.len() >= self.capacity {
+                let soonest = entries
+                    .iter()
+                    .min_by_key(|(_, entry)| entry.expires_at)
+                    .map(|(number, _)| number.clone());
+                if let Some(soonest) = soonest {
+                    entries.remove(&soonest);
+                }
+            }
+        }
+        entries.insert(
+            number.to_string(),
+            CacheEntry {
+                routing,
+                fetched_at: self.clock.now(),
+                expires_at: now + self.ttl,
+            },
+        );
+        gauge!("lnp_cache_entries").set(entries.len() as f64);
+    }
+
+    /// Forget `number`; whether it was cached
+    pub fn remove(&self, number: &str) -> bool {
+        let mut entries = self.entries.lock().unwrap();
+        let removed = entries.remove(number).is_some();
+        gauge!("lnp_cache_entries").set(entries.len() as f64);
+        removed
+    }
+
+    pub fn stats(&self) -> CacheStats {
+        CacheStats {
+            entries: self.entries.lock().unwrap().len(),
+            hits: self.hits.load(Ordering::Relaxed),
+            misses: self.misses.load(Ordering::Relaxed),
+        }
+    }
+}
+
+/// Routing overrides of dialed numbers, cached and within a time budget
+pub struct LnpResolver {
+    lookup: Arc<dyn RoutingLookup>,
+    cache: Arc<LnpCache>,
+    plan: NumberingPlan,
+    budget: Duration,
+    ttl: Duration,
+    capacity: usize,
+}
+
+impl LnpResolver {
+    pub fn new(config: &LnpConfig, lookup: Arc<dyn RoutingLookup>, plan: NumberingPlan) -> Self {
+        let ttl = Duration::from_secs(config.cache_ttl_secs);
+        Self {
+            lookup,
+            cache: Arc::new(LnpCache::new(ttl, config.cache_capacity, system_clock())),
+            plan,
+            budget: Duration::from_millis(config.call_budget_ms),
+            ttl,
+            capacity: config.cache_capacity,
+        }
+    }
+
+    /// Expire cache entries by `clock` (starts with an empty cache)
+    pub fn with_clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.cache = Arc::new(LnpCache::new(self.ttl, self.capacity, clock));
+        self
+    }
+
+    /// E.164 form of a dialed number, the cache key
+    pub fn normalize(&self, number: &str, tenant: Option<&str>) -> Option<String> {
+        self.plan.normalize(number, tenant)
+    }
+
+    /// Override of a call to `number`, dialed in `tenant`
+    ///
+    /// `None` when the number is not a phone number, is not ported, or the
+    /// lookup failed or did not answer within the call budget.
+    pub async fn resolve(&self, number: &str, tenant: Option<&str>) -> Option<RoutingOverride> {
+        let e164 = self.normalize(number, tenant)?;
+        if let Some(routing) = self.cache.get(&e164) {
+            return (!routing.is_none()).then_some(routing);
+        }
+
+        let lookup = self.lookup.clone();
+        let cache = self.cache.clone();
+        let key = e164.clone();
+        // Spawned so an answer arriving after the budget still fills the cache
+        let task = tokio::spawn(async move {
+            let started = Instant::now();
+            let result = lookup.lookup(&key).await;
+            histogram!("lnp_lookup_duration_seconds").record(started.elapsed().as_secs_f64());
+            match result {
+                Ok(routing) => {
+                    let outcome = if routing.is_none() { "not_ported" } else { "ported" };
+                    counter!("lnp_lookups_total", "outcome" => outcome).increment(1);
+                    cache.insert(&key, routing.clone());
+                    Some(routing)
+                }
+                Err(e) => {
+                    counter!("lnp_lookups_total", "outcome" => "error").increment(1);
+                    warn!("LNP lookup of {} failed: {}", key, e);
+                    None
+                }
+            }
+        });
+
+        match tokio::time::timeout(self.budget, task).await {
+            Ok(Ok(routing)) => routing.filter(|routing| !routing.is_none()),
+            Ok(Err(e)) => {
+                warn!("LNP lookup of {} aborted: {}", e164, e);
+                None
+            }
+            Err(_) => {
+                counter!("lnp_lookups_total", "outcome" => "over_budget").increment(1);
+                debug!(
+                    "LNP lookup of {} exceeded the {:?} budget; routing as dialed",
+                    e164, self.budget
+                );
+                None
+            }
+        }
+    }
+
+    /// Cached answer for `number`, dialed in `tenant`
+    pub fn cached(&self, number: &str, tenant: Option<&str>) -> Option<CachedRoute> {
+        self.cache.entry(&self.normalize(number, tenant)?)
+    }
+
+    /// Forget the cached answer for `number`; whether there was one
+    pub fn flush(&self, number: &str, tenant: Option<&str>) -> bool {
+        self.normalize(number, tenant)
+            .is_some_and(|e164| self.cache.remove(&e164))
+    }
+
+    pub fn stats(&self) -> CacheStats {
+        self.cache.stats()
+    }
+}
+
+#[async_trait]
+impl Explain for LnpResolver {
+    fn step(&self) -> &'static str {
+        "lnp"
+    }
+
+    async fn explain(&self, call: &RoutedCall) -> Option<Explanation> {
+        let tenant = realm_of(&call.caller_uri);
+        let routing = self.resolve(call.dialed(), tenant).await?;
+        let mut explanation = Explanation::matched(format!(
+            "{} is ported",
+            self.normalize(call.dialed(), tenant)?
+        ));
+        if let Some(routing_number) = &routing.routing_number {
+            explanation =
+                explanation.with_target(with_routing_number(&call.target_uri, routing_number));
+        }
+        if let Some(trunk) = routing.trunk {
+            explanation = explanation.with_trunk(trunk);
+        }
+        Some(explanation)
+    }
+}
+
+/// `uri` with the RFC 4694 routing number (`rn`) and "dip done" (`npdi`)
+/// parameters in its user part; unchanged when a dip already happened
+pub fn with_routing_number(uri: &str, routing_number: &str) -> String {
+    let end = uri.find('@').unwrap_or(uri.len());
+    let user = &uri[..end];
+    if user.contains(";npdi") || user.contains(";rn=") {
+        return uri.to_string();
+    }
+    format!("{};rn={};npdi{}", user, routing_number, &uri[end..])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::ManualClock;
+    use async_trait::async_trait;
+    use std::sync::atomic::AtomicUsize;
+
+    /// Lookup answering after `delay`, counting its calls
+    struct SlowLookup {
+        delay: Duration,
+        calls: AtomicUsize,
+    }
+
+    #[async_trait]
+    impl RoutingLookup for SlowLookup {
+        async fn lookup(&self, number: &str) -> Result<RoutingOverride, String> {
+            self.calls.fetch_add(1, Ordering::SeqCst);
+            tokio::time::sleep(self.delay).await;
+            Ok(RoutingOverride {
+                routing_number: Some(format!("{}0", &number[..number.len() - 1])),
+                trunk: None,
+            })
+        }
+    }
+
+    fn config() -> LnpConfig {
+        LnpConfig {
+            call_budget_ms: 50,
+            cache_ttl_secs: 60,
+            ..Default::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn test_over_budget_routes_as_dialed_then_hits_cache() {
+        let lookup = Arc::new(SlowLookup {
+            delay: Duration::from_millis(200),
+            calls: AtomicUsize::new(0),
+        });
+        let resolver = LnpResolver::new(&config(), lookup.clone(), NumberingPlan::default());
+
+        let started = Instant::now();
+        assert_eq!(resolver.resolve("(415) 555-1234", None).await, None);
+        assert!(started.elapsed() < Duration::from_millis(150));
+
+        // The late answer still lands in the cache
+        tokio::time::sleep(Duration::from_millis(300)).await;
+        let routing = resolver.resolve("+1 415 555 1234", None).await.unwrap();
+        assert_eq!(routing.routing_number.as_deref(), Some("+14155551230"));
+        assert_eq!(lookup.calls.load(Ordering::SeqCst), 1);
+        assert_eq!(resolver.stats().hits, 1);
+
+        // Extensions are not looked up
+        assert_eq!(resolver.resolve("1001", None).await, None);
+        assert_eq!(lookup.calls.load(Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn test_cache_expiry_and_flush() {
+        let clock = Arc::new(ManualClock::new());
+        let lookup = Arc::new(SlowLookup {
+            delay: Duration::ZERO,
+            calls: AtomicUsize::new(0),
+        });
+        let resolver = LnpResolver::new(&config(), lookup.clone(), NumberingPlan::default())
+            .with_clock(clock.clone());
+
+        assert!(resolver.resolve("+14155551234", None).await.is_some());
+        clock.advance(Duration::from_secs(59));
+        assert_eq!(resolver.cached("+14155551234", None).unwrap().expires_in_secs, 1);
+        assert!(resolver.resolve("+14155551234", None).await.is_some());
+        assert_eq!(lookup.calls.load(Ordering::SeqCst), 1);
+
+        clock.advance(Duration::from_secs(2));
+        assert!(resolver.cached("+14155551234", None).is_none());
+        assert!(resolver.resolve("+14155551234", None).await.is_some());
+        assert_eq!(lookup.calls.load(Ordering::SeqCst), 2);
+
+        assert!(resolver.flush("4155551234", None));
+        assert!(!resolver.flush("4155551234", None));
+    }
+
+    #[test]
+    fn test_capacity_evicts_soonest_expiry() {
+        let clock = Arc::new(ManualClock::new());
+        let cache = LnpCache::new(Duration::from_secs(60), 2, clock.clone());
+        cache.insert("+1", RoutingOverride::none());
+        clock.advance(Duration::from_secs(1));
+        cache.insert("+2", RoutingOverride::none());
+        cache.insert("+3", RoutingOverride::none());
+        assert!(cache.entry("+1").is_none());
+        assert!(cache.entry("+2").is_some());
+        assert_eq!(cache.stats().entries, 2);
+    }
+}
@@ -0,0 +1,142 @@
+//! In-memory log ring buffer
+//!
+//! A tracing layer that keeps the most recent formatted log lines so they
+//! can be included in diagnostic bundles without access to the host's log
+//! files.
+
+use chrono::Utc;
+use std::collections::VecDeque;
+use std::fmt::{self, Write};
+use std::sync::{Arc, Mutex};
+use tracing::field::{Field, Visit};
+use tracing::{Event, Subscriber};
+use tracing_subscriber::layer::{Context, Layer};
+
+/// Default number of log lines kept
+pub const DEFAULT_LOG_BUFFER_LINES: usize = 2000;
+
+/// Most recent log lines, oldest first
+pub struct LogRingBuffer {
+    lines: Mutex<VecDeque<String>>,
+    capacity: usize,
+}
+
+impl LogRingBuffer {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            lines: Mutex::new(VecDeque::with_capacity(capacity)),
+            capacity,
+        }
+    }
+
+    /// Append a line, dropping the oldest when full
+    pub fn push(&self, line: String) {
+        let mut lines = self.lines.lock().unwrap();
+        if lines.len() == self.capacity {
+            lines.pop_front();
+        }
+        lines.push_back(line);
+    }
+
+    /// The last `count` lines, oldest first
+    pub fn tail(&self, count: usize) -> Vec<String> {
+        let lines = self.lines.lock().unwrap();
+        lines
+            .iter()
+            .skip(lines.len().saturating_sub(count))
+            .cloned()
+            .collect()
+    }
+
+    pub fn len(&self) -> usize {
+        self.lines.lock().unwrap().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Tracing layer feeding this buffer
+    pub fn layer(self: &Arc<Self>) -> LogBufferLayer {
+        LogBufferLayer {
+            buffer: self.clone(),
+        }
+    }
+}
+
+impl Default for LogRingBuffer {
+    fn default() -> Self {
+        Self::new(DEFAULT_LOG_BUFFER_LINES)
+    }
+}
+
+/// Tracing layer recording events into a [`LogRingBuffer`]
+pub struct LogBufferLayer {
+    buffer: Arc<LogRingBuffer>,
+}
+
+impl<S: Subscriber> Layer<S> for LogBufferLayer {
+    fn on_event(&self, event: &Event<'_>, _ctx: Context<'_, S>) {
+        self.buffer.push(format_event(event));
+    }
+}
+
+/// Log line of an event: time, level, target, message and fields
+pub(crate) fn format_event(event: &Event<'_>) -> String {
+    let metadata = event.metadata();
+    let mut line = format!(
+        "{} {:>5} {}:",
+        Utc::now().to_rfc3339(),
+        metadata.level(),
+        metadata.target()
+    );
+    event.record(&mut LineVisitor(&mut line));
+    line
+}
+
+/// Appends an event's message and fields to a line
+struct LineVisitor<'a>(&'a mut String);
+
+impl Visit for LineVisitor<'_> {
+    fn record_str(&mut self, field: &Field, value: &str) {
+        if field.name() == "message" {
+            let _ = write!(self.0, " {}", value);
+        } else {
+            let _ = write!(self.0, " {}={}", field.name(), value);
+        }
+    }
+
+    fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+        if field.name() == "message" {
+            let _ = write!(self.0, " {:?}", value);
+        } else {
+            let _ = write!(self.0, " {}={:?}", field.name(), value);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tracing_subscriber::prelude::*;
+
+    #[test]
+    fn test_layer_keeps_last_lines() {
+        let buffer = Arc::new(LogRingBuffer::new(2));
+        let subscriber = tracing_subscriber::registry().with(buffer.layer());
+
+        tracing::subscriber::with_default(subscriber, || {
+            tracing::info!("first");
+            tracing::warn!(call_id = "abc", "second");
+            tracing::info!("third");
+        });
+
+        let lines = buffer.tail(10);
+        assert_eq!(lines.len(), 2);
+        assert!(lines[0].contains("WARN yakyak::infrastructure::logging::tests:"));
+        assert!(lines[0].contains(" second"));
+        assert!(lines[0].contains(" call_id=abc"));
+        assert!(lines[1].ends_with("third"));
+        assert_eq!(buffer.tail(1), vec![lines[1].clone()]);
+    }
+}
@@ -0,0 +1,432 @@
+//! Receiver-side bandwidth estimation of WebRTC legs
+//!
+//! A browser on a poor network keeps sending at the rate it started with
+//! unless told otherwise, and its late packets are useless to a conference.
+//! For a leg that negotiated `goog-remb`, [`BandwidthEstimator`] measures
+//! the loss and interarrival jitter (RFC 3550) of the incoming stream and
+//! once per update adjusts an estimate of the bitrate the path carries:
+//! cut in proportion to the loss above 10%, cut by 15% when jitter rises
+//! sharply, raised by 8% while loss stays under 2%. The stream sends the
+//! estimate back in REMB packets, which the browser caps its Opus bitrate
+//! to. The loss the browser reports on our media drives our send bitrate
+//! the same way, for encoders to follow.
+//!
+//! A leg whose estimate falls below `usable_bitrate` either way raises a
+//! [`QualityWarning`] once, until it recovers.
+
+use super::rtp::{Remb, RtpPacket};
+use metrics::counter;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex, Weak};
+use std::time::{Duration, Instant};
+use tokio::sync::broadcast;
+use tracing::warn;
+
+/// Shortest time between estimate updates
+pub const UPDATE_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Loss above which the estimate is cut
+const HIGH_LOSS: f64 = 0.10;
+
+/// Loss below which the estimate grows
+const LOW_LOSS: f64 = 0.02;
+
+/// Growth per update while loss is low
+const INCREASE: f64 = 1.08;
+
+/// Cut when jitter rises sharply
+const OVERUSE_DECREASE: f64 = 0.85;
+
+/// Jitter, in milliseconds, from which a sharp rise counts as overuse
+const OVERUSE_JITTER_MS: f64 = 30.0;
+
+/// Bounds and thresholds of the estimates
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct BandwidthConfig {
+    /// Estimate WebRTC legs that offer goog-remb
+    pub enabled: bool,
+    /// Lowest estimate, in bits per second
+    pub min_bitrate: u64,
+    /// Highest estimate, in bits per second
+    pub max_bitrate: u64,
+    /// Estimate of a new leg, in bits per second
+    pub start_bitrate: u64,
+    /// Estimate below which a leg is warned about, in bits per second
+    pub usable_bitrate: u64,
+}
+
+impl Default for BandwidthConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            min_bitrate: 6_000,
+            max_bitrate: 64_000,
+            start_bitrate: 32_000,
+            usable_bitrate: 12_000,
+        }
+    }
+}
+
+impl BandwidthConfig {
+    pub fn validate(&self) -> Result<(), String> {
+        if self.min_bitrate == 0 || self.min_bitrate > self.max_bitrate {
+            return Err("min_bitrate must be positive and at most max_bitrate".to_string());
+        }
+        if !(self.min_bitrate..=self.max_bitrate).contains(&self.start_bitrate) {
+            return Err("start_bitrate must be between min_bitrate and max_bitrate".to_string());
+        }
+        Ok(())
+    }
+}
+
+/// Loss-based rate control, the same for both directions
+#[derive(Debug, Clone)]
+struct RateControl {
+    bitrate: f64,
+    min: f64,
+    max: f64,
+}
+
+impl RateControl {
+    fn new(config: &BandwidthConfig) -> Self {
+        Self {
+            bitrate: config.start_bitrate as f64,
+            min: config.min_bitrate as f64,
+            max: config.max_bitrate as f64,
+        }
+    }
+
+    fn update(&mut self, loss: f64, overuse: bool) {
+        if loss > HIGH_LOSS {
+            self.bitrate *= 1.0 - 0.5 * loss;
+        } else if overuse {
+            self.bitrate *= OVERUSE_DECREASE;
+        } else if loss < LOW_LOSS {
+            self.bitrate *= INCREASE;
+        }
+        self.bitrate = self.bitrate.clamp(self.min, self.max);
+    }
+
+    fn bitrate(&self) -> u64 {
+        self.bitrate as u64
+    }
+}
+
+/// Current estimates of a leg
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub struct BandwidthEstimate {
+    /// Most we ask the remote to send, in bits per second
+    pub receive_bitrate: u64,
+    /// Most we should send, from the remote's loss reports
+    pub send_bitrate: u64,
+    /// Share of incoming packets lost in the last update
+    pub loss: f64,
+    /// Interarrival jitter of incoming packets, in milliseconds
+    pub jitter_ms: f64,
+    /// Whether either estimate is below `usable_bitrate`
+    pub capped: bool,
+}
+
+/// Raised when a leg's estimate falls below `usable_bitrate`
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct QualityWarning {
+    pub call_id: String,
+    pub receive_bitrate: u64,
+    pub send_bitrate: u64,
+    pub usable_bitrate: u64,
+}
+
+/// Bandwidth estimation of one leg
+#[derive(Debug)]
+pub struct BandwidthEstimator {
+    call_id: String,
+    config: BandwidthConfig,
+    receive: RateControl,
+    send: RateControl,
+    /// Source the counters are about
+    ssrc: Option<u32>,
+    /// Highest extended sequence number received
+    max_sequence: Option<u32>,
+    /// `max_sequence` before the first packet of the current update
+    update_base: Option<u32>,
+    received: u32,
+    /// Interarrival jitter in timestamp units (RFC 3550 A.8)
+    jitter: f64,
+    clock_rate: u32,
+    /// Arrival time and RTP timestamp of the previous packet
+    last_arrival: Option<(Instant, u32)>,
+    last_update: Instant,
+    previous_jitter_ms: f64,
+    loss: f64,
+    warned: bool,
+    events: Option<broadcast::Sender<QualityWarning>>,
+}
+
+impl BandwidthEstimator {
+    pub fn new(call_id: impl Into<String>, config: BandwidthConfig, now: Instant) -> Self {
+        Self {
+            call_id: call_id.into(),
+            receive: RateControl::new(&config),
+            send: RateControl::new(&config),
+            config,
+            ssrc: None,
+            max_sequence: None,
+            update_base: None,
+            received: 0,
+            jitter: 0.0,
+            clock_rate: 8000,
+            last_arrival: None,
+            last_update: now,
+            previous_jitter_ms: 0.0,
+            loss: 0.0,
+            warned: false,
+            events: None,
+        }
+    }
+
+    /// Send quality warnings to `events`
+    pub fn with_events(mut self, events: broadcast::Sender<QualityWarning>) -> Self {
+        self.events = Some(events);
+        self
+    }
+
+    /// Account for an RTP packet received at `now`
+    pub fn on_rtp(&mut self, now: Instant, packet: &RtpPacket, clock_rate: u32) {
+        self.on_packet(now, packet.ssrc, packet.sequence, packet.timestamp, clock_rate);
+    }
+
+    fn on_packet(&mut self, now: Instant, ssrc: u32, sequence: u16, timestamp: u32, clock_rate: u32) {
+        if self.ssrc != Some(ssrc) {
+            self.ssrc = Some(ssrc);
+            self.max_sequence = None;
+            self.update_base = None;
+            self.received = 0;
+            self.jitter = 0.0;
+            self.last_arrival = None;
+        }
+        self.clock_rate = clock_rate.max(1);
+
+        // Extend the sequence number to the cycle closest to the highest
+        let extended = match self.max_sequence {
+            None => sequence as u32,
+            Some(max) => {
+                let delta = sequence.wrapping_sub(max as u16) as i16 as i64;
+                (max as i64 + delta).max(0) as u32
+            }
+        };
+        if self.update_base.is_none() {
+            self.update_base = Some(extended.saturating_sub(1));
+        }
+        self.max_sequence = Some(self.max_sequence.map_or(extended, |max| max.max(extended)));
+        self.received += 1;
+
+        if let Some((arrived, last_timestamp)) = self.last_arrival {
+            let arrival = now.saturating_duration_since(arrived).as_secs_f64() * self.clock_rate as f64;
+            let sent = timestamp.wrapping_sub(last_timestamp) as i32 as f64;
+            self.jitter += ((arrival - sent).abs() - self.jitter) / 16.0;
+        }
+        self.last_arrival = Some((now, timestamp));
+    }
+
+    /// The remote's receiver report on our media, `fraction_lost` out of
+    /// 256
+    pub fn on_report(&mut self, fraction_lost: u8) {
+        self.send.update(fraction_lost as f64 / 256.0, false);
+        self.check_quality();
+    }
+
+    /// Update the receive estimate when an update is due; the REMB to send
+    /// the remote then
+    pub fn update(&mut self, now: Instant, sender_ssrc: u32) -> Option<Remb> {
+        if now.saturating_duration_since(self.last_update) < UPDATE_INTERVAL {
+            return None;
+        }
+        self.last_update = now;
+        let (ssrc, max, base) = (self.ssrc?, self.max_sequence?, self.update_base?);
+
+        // Nothing arrived: no evidence either way
+        let expected = max.saturating_sub(base);
+        if expected > 0 {
+            self.loss = expected.saturating_sub(self.received) as f64 / expected as f64;
+            let jitter_ms = self.jitter_ms();
+            let overuse =
+                jitter_ms > OVERUSE_JITTER_MS && jitter_ms > self.previous_jitter_ms * 1.5;
+            self.previous_jitter_ms = jitter_ms;
+            self.receive.update(self.loss, overuse);
+            self.check_quality();
+        }
+        self.update_base = Some(max);
+        self.received = 0;
+
+        Some(Remb::new(sender_ssrc, self.receive.bitrate(), vec![ssrc]))
+    }
+
+    fn jitter_ms(&self) -> f64 {
+        self.jitter * 1000.0 / self.clock_rate as f64
+    }
+
+    fn is_capped(&self) -> bool {
+        self.receive.bitrate().min(self.send.bitrate()) < self.config.usable_bitrate
+    }
+
+    fn check_quality(&mut self) {
+        let capped = self.is_capped();
+        if capped && !self.warned {
+            warn!(
+                "Call {} capped below a usable bitrate: receiving {} bps, sending {} bps",
+                self.call_id,
+                self.receive.bitrate(),
+                self.send.bitrate()
+            );
+            counter!("media_bandwidth_warnings_total").increment(1);
+            if let Some(events) = &self.events {
+                // No subscriber is not an error
+                let _ = events.send(QualityWarning {
+                    call_id: self.call_id.clone(),
+                    receive_bitrate: self.receive.bitrate(),
+                    send_bitrate: self.send.bitrate(),
+                    usable_bitrate: self.config.usable_bitrate,
+                });
+            }
+        }
+        self.warned = capped;
+    }
+
+    /// Bitrate our encoder should not exceed toward this leg
+    pub fn send_bitrate(&self) -> u64 {
+        self.send.bitrate()
+    }
+
+    pub fn estimate(&self) -> BandwidthEstimate {
+        BandwidthEstimate {
+            receive_bitrate: self.receive.bitrate(),
+            send_bitrate: self.send.bitrate(),
+            loss: self.loss,
+            jitter_ms: self.jitter_ms(),
+            capped: self.is_capped(),
+        }
+    }
+}
+
+/// Estimators of the legs being estimated, by Call-ID
+pub struct BandwidthMonitor {
+    config: BandwidthConfig,
+    /// Dropped with their stream
+    estimators: Mutex<HashMap<String, Weak<Mutex<BandwidthEstimator>>>>,
+    events: broadcast::Sender<QualityWarning>,
+}
+
+impl BandwidthMonitor {
+    pub fn new(config: BandwidthConfig) -> Self {
+        let (events, _) = broadcast::channel(256);
+        Self {
+            config,
+            estimators: Mutex::new(HashMap::new()),
+            events,
+        }
+    }
+
+    pub fn config(&self) -> &BandwidthConfig {
+        &self.config
+    }
+
+    /// Receive quality warnings
+    pub fn subscribe(&self) -> broadcast::Receiver<QualityWarning> {
+        self.events.subscribe()
+    }
+
+    /// Start estimating the leg of `call_id`, until the estimator is dropped
+    pub fn register(&self, call_id: &str) -> Arc<Mutex<BandwidthEstimator>> {
+        let estimator = BandwidthEstimator::new(call_id, self.config.clone(), Instant::now())
+            .with_events(self.events.clone());
+        let estimator = Arc::new(Mutex::new(estimator));
+        let mut estimators = self.estimators.lock().unwrap();
+        estimators.retain(|_, estimator| estimator.strong_count() > 0);
+        estimators.insert(call_id.to_string(), Arc::downgrade(&estimator));
+        estimator
+    }
+
+    /// Current estimates of the leg of `call_id`
+    pub fn estimate(&self, call_id: &str) -> Option<BandwidthEstimate> {
+        let estimator = self.estimators.lock().unwrap().get(call_id)?.upgrade()?;
+        let estimate = estimator.lock().unwrap().estimate();
+        Some(estimate)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Feed one second of 20ms packets, dropping `lost` of every 10; the
+    /// REMB bitrate sent at its end
+    fn second(
+        estimator: &mut BandwidthEstimator,
+        start: Instant,
+        second: u64,
+        lost: u16,
+    ) -> u64 {
+        for n in 0..50u16 {
+            let index = second as u16 * 50 + n;
+            if index % 10 < lost {
+                continue;
+            }
+            let now = start + Duration::from_millis(index as u64 * 20);
+            estimator.on_packet(now, 0x5EED, index.wrapping_add(65_200), index as u32 * 160, 8000);
+        }
+        let end = start + Duration::from_secs(second + 1);
+        estimator.update(end, 0x1234).unwrap().bitrate
+    }
+
+    #[test]
+    fn test_remb_follows_loss() {
+        let monitor = BandwidthMonitor::new(BandwidthConfig::default());
+        let mut warnings = monitor.subscribe();
+        let leg = monitor.register("call-1");
+        let mut estimator = leg.lock().unwrap();
+        let start = Instant::now();
+
+        // 30% loss, across a sequence number wrap
+        let mut previous = BandwidthConfig::default().start_bitrate;
+        for s in 0..8 {
+            let bitrate = second(&mut estimator, start, s, 3);
+            assert!(bitrate < previous, "{} not below {}", bitrate, previous);
+            previous = bitrate;
+        }
+        assert!((estimator.estimate().loss - 0.3).abs() < 0.05);
+        assert!(estimator.estimate().jitter_ms < 1.0);
+        let warning = warnings.try_recv().unwrap();
+        assert_eq!(warning.call_id, "call-1");
+        assert!(warning.receive_bitrate < warning.usable_bitrate);
+        assert!(estimator.estimate().capped);
+
+        // The network recovers
+        for s in 8..20 {
+            let bitrate = second(&mut estimator, start, s, 0);
+            assert!(bitrate > previous);
+            previous = bitrate;
+        }
+        drop(estimator);
+        assert_eq!(monitor.estimate("call-1").map(|e| e.capped), Some(false));
+        // Warned once per episode
+        assert!(warnings.try_recv().is_err());
+    }
+
+    #[test]
+    fn test_send_bitrate_follows_reports() {
+        let mut estimator =
+            BandwidthEstimator::new("call-2", BandwidthConfig::default(), Instant::now());
+        let start = estimator.send_bitrate();
+        // About 30% lost
+        estimator.on_report(77);
+        estimator.on_report(77);
+        let cut = estimator.send_bitrate();
+        assert!(cut < start);
+        estimator.on_report(0);
+        assert!(estimator.send_bitrate() > cut);
+        // No REMB before anything was received
+        assert!(estimator.update(Instant::now() + UPDATE_INTERVAL, 1).is_none());
+    }
+}
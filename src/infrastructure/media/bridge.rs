@@ -2,14 +2,38 @@
 //!
 //! Bridges media between two endpoints (caller and callee)
 
+use super::ptime::{PacketFormat, PtimeAdapter};
+use super::rtp::{RtpPacket, RtpStats};
 use super::stream::MediaStream;
-use std::sync::Arc;
+use super::tap::MediaTap;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
 use tokio::sync::RwLock;
 use tracing::{info, warn};
 
+/// Direction of packets through a bridge
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BridgeDirection {
+    /// Received on leg A, sent on leg B
+    AToB,
+    /// Received on leg B, sent on leg A
+    BToA,
+}
+
+/// One side of a bridge
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BridgeLeg {
+    /// The caller's side
+    A,
+    /// The callee's side
+    B,
+}
+
 /// Media Bridge
 ///
-/// Connects two media streams and forwards packets between them
+/// Connects two media streams and forwards packets between them. Each leg
+/// exchanges RTCP with its own endpoint, multiplexed on the RTP port or not
+/// as negotiated on that leg, so the legs need not agree.
 pub struct MediaBridge {
     /// Stream for leg A (caller)
     leg_a: Arc<MediaStream>,
@@ -17,6 +41,15 @@ pub struct MediaBridge {
     leg_b: Arc<MediaStream>,
     /// Bridge active flag
     active: Arc<RwLock<bool>>,
+    /// Set once by `close()`
+    closed: AtomicBool,
+    /// Negotiated codec and packet time of leg A and leg B
+    formats: Option<(PacketFormat, PacketFormat)>,
+    /// Re-packetizers, present when the legs' formats differ
+    a_to_b: Mutex<Option<PtimeAdapter>>,
+    b_to_a: Mutex<Option<PtimeAdapter>>,
+    /// Decoded audio of a leg for live captions and the like
+    taps: Mutex<Vec<MediaTap>>,
 }
 
 impl MediaBridge {
@@ -32,6 +65,77 @@ impl MediaBridge {
             leg_a,
             leg_b,
             active: Arc::new(RwLock::new(false)),
+            closed: AtomicBool::new(false),
+            formats: None,
+            a_to_b: Mutex::new(None),
+            b_to_a: Mutex::new(None),
+            taps: Mutex::new(Vec::new()),
+        }
+    }
+
+    /// Negotiated codec and packet time of each leg
+    ///
+    /// When they differ, packets are re-packetized to the packet time (and
+    /// codec) of the leg they are sent on.
+    pub fn with_packet_formats(mut self, leg_a: PacketFormat, leg_b: PacketFormat) -> Self {
+        let adapter = |source: PacketFormat, target: PacketFormat, ssrc: u32| {
+            if source == target {
+                None
+            } else {
+                PtimeAdapter::new(source, target, ssrc)
+            }
+        };
+        if leg_a != leg_b {
+            info!(
+                "Media bridge adapts A({} PT {}ms) <-> B({} PT {}ms)",
+                leg_a.payload_type, leg_a.ptime_ms, leg_b.payload_type, leg_b.ptime_ms
+            );
+        }
+        self.a_to_b = Mutex::new(adapter(leg_a, leg_b, self.leg_b.ssrc()));
+        self.b_to_a = Mutex::new(adapter(leg_b, leg_a, self.leg_a.ssrc()));
+        self.formats = Some((leg_a, leg_b));
+        self
+    }
+
+    /// Negotiated codec and packet time of leg A and leg B, if known
+    pub fn packet_formats(&self) -> Option<(PacketFormat, PacketFormat)> {
+        self.formats
+    }
+
+    /// Whether leg A and leg B multiplex RTCP on their RTP port
+    pub fn rtcp_mux(&self) -> (bool, bool) {
+        (self.leg_a.is_rtcp_muxed(), self.leg_b.is_rtcp_muxed())
+    }
+
+    /// Offer the audio a leg receives to `tap` until it is closed
+    pub fn add_tap(&self, tap: MediaTap) {
+        self.taps.lock().unwrap().push(tap);
+    }
+
+    /// Packets to send on the other leg for one received on a leg
+    ///
+    /// Forwarded unchanged unless the legs' formats differ. Taps on the
+    /// receiving leg get the packet first.
+    pub fn adapt(&self, direction: BridgeDirection, packet: RtpPacket) -> Vec<RtpPacket> {
+        let leg = match direction {
+            BridgeDirection::AToB => BridgeLeg::A,
+            BridgeDirection::BToA => BridgeLeg::B,
+        };
+        {
+            let mut taps = self.taps.lock().unwrap();
+            taps.retain(|tap| !tap.is_closed());
+            for tap in taps.iter().filter(|tap| tap.leg() == leg) {
+                tap.offer(&packet);
+            }
+        }
+
+        let adapter = match direction {
+            BridgeDirection::AToB => &self.a_to_b,
+            BridgeDirection::BToA => &self.b_to_a,
+        };
+        match adapter.lock().unwrap().as_mut() {
+            Some(adapter) => adapter.push(&packet),
+            None => vec![packet],
         }
     }
 
@@ -71,6 +175,87 @@ impl MediaBridge {
     pub async fn is_active(&self) -> bool {
         *self.active.read().await
     }
+
+    /// Close both legs, releasing their tasks and ports
+    ///
+    /// Safe to call more than once.
+    pub async fn close(&self) {
+        if self.closed.swap(true, Ordering::SeqCst) {
+            return;
+        }
+        *self.active.write().await = false;
+        self.leg_a.close().await;
+        self.leg_b.close().await;
+        info!("Media bridge closed");
+    }
+
+    /// Bridge `stream` in place of one leg, e.g. when another device takes
+    /// over the call
+    ///
+    /// The other leg keeps running in the returned bridge, so the far end
+    /// is not interrupted; `stream` must be started by the caller. This
+    /// bridge is retired without closing anything, and the replaced stream
+    /// is returned for the caller to close. A bridge looping one stream back
+    /// to itself gets `stream` on both sides.
+    pub async fn replace_leg(
+        &self,
+        leg: BridgeLeg,
+        stream: Arc<MediaStream>,
+        format: PacketFormat,
+    ) -> (MediaBridge, Arc<MediaStream>) {
+        let (replaced, kept, kept_format) = match leg {
+            BridgeLeg::A => (&self.leg_a, &self.leg_b, self.formats.map(|(_, b)| b)),
+            BridgeLeg::B => (&self.leg_b, &self.leg_a, self.formats.map(|(a, _)| a)),
+        };
+        let (kept, kept_format) = if Arc::ptr_eq(replaced, kept) {
+            (stream.clone(), format)
+        } else {
+            (kept.clone(), kept_format.unwrap_or(format))
+        };
+        let bridge = match leg {
+            BridgeLeg::A => {
+                MediaBridge::new(stream, kept).with_packet_formats(format, kept_format)
+            }
+            BridgeLeg::B => {
+                MediaBridge::new(kept, stream).with_packet_formats(kept_format, format)
+            }
+        };
+        let active = std::mem::replace(&mut *self.active.write().await, false);
+        *bridge.active.write().await = active;
+        // Taps follow the leg, not the device on it
+        *bridge.taps.lock().unwrap() = std::mem::take(&mut *self.taps.lock().unwrap());
+        self.closed.store(true, Ordering::SeqCst);
+        info!("Media bridge leg {:?} replaced", leg);
+        (bridge, replaced.clone())
+    }
+
+    /// Stop relaying without closing either leg, e.g. when the legs go on
+    /// in another bridge or a conference
+    pub async fn retire(&self) {
+        *self.active.write().await = false;
+        self.closed.store(true, Ordering::SeqCst);
+        info!("Media bridge retired");
+    }
+
+    /// Whether `close()` or `retire()` was called
+    pub fn is_closed(&self) -> bool {
+        self.closed.load(Ordering::SeqCst)
+    }
+
+    /// Statistics of leg A and leg B
+    pub fn stats(&self) -> (RtpStats, RtpStats) {
+        (self.leg_a.stats(), self.leg_b.stats())
+    }
+}
+
+impl Drop for MediaBridge {
+    fn drop(&mut self) {
+        if !self.is_closed() {
+            warn!("Media bridge dropped without close()");
+            self.leg_a.release();
+            self.leg_b.release();
+        }
+    }
 }
 
 /// Media Bridge Manager
@@ -109,7 +294,7 @@ impl MediaBridgeManager {
         let mut bridges = self.bridges.write().await;
 
         if let Some(bridge) = bridges.remove(call_id) {
-            bridge.stop().await;
+            bridge.close().await;
             info!("Removed bridge for call: {}", call_id);
         } else {
             warn!("No bridge found for call: {}", call_id);
@@ -164,4 +349,177 @@ mod tests {
         manager.remove_bridge("test-call-1").await;
         assert_eq!(manager.active_count().await, 0);
     }
+
+    #[tokio::test]
+    async fn test_replace_leg_keeps_other_leg() {
+        let stream_a = Arc::new(MediaStream::new(10100, 0, 8000).await.unwrap());
+        let stream_b = Arc::new(MediaStream::new(10110, 0, 8000).await.unwrap());
+        let stream_c = Arc::new(MediaStream::new(10120, 0, 8000).await.unwrap());
+        let format = PacketFormat::new(0, 20);
+
+        let bridge = MediaBridge::new(stream_a.clone(), stream_b.clone())
+            .with_packet_formats(format, format);
+        bridge.start().await.unwrap();
+        let (replacement, replaced) = bridge
+            .replace_leg(BridgeLeg::B, stream_c.clone(), format)
+            .await;
+
+        assert!(Arc::ptr_eq(&replaced, &stream_b));
+        assert!(bridge.is_closed());
+        assert!(replacement.is_active().await);
+
+        drop(bridge);
+        replaced.close().await;
+        replacement.close().await;
+    }
+
+    #[tokio::test]
+    async fn test_bridge_close_is_idempotent() {
+        let stream_a = Arc::new(MediaStream::new(10050, 0, 8000).await.unwrap());
+        let stream_b = Arc::new(MediaStream::new(10060, 0, 8000).await.unwrap());
+
+        let bridge = MediaBridge::new(stream_a.clone(), stream_b.clone());
+        bridge.start().await.unwrap();
+        bridge.close().await;
+        bridge.close().await;
+
+        assert!(!bridge.is_active().await);
+        assert!(stream_a.is_closed());
+        assert!(stream_b.is_closed());
+    }
+
+    #[tokio::test]
+    async fn test_bridge_repacketizes_40ms_to_20ms() {
+        let stream_a = Arc::new(MediaStream::new(10070, 0, 8000).await.unwrap());
+        let stream_b = Arc::new(MediaStream::new(10080, 0, 8000).await.unwrap());
+        let ssrc_b = stream_b.ssrc();
+
+        let bridge = MediaBridge::new(stream_a, stream_b)
+            .with_packet_formats(PacketFormat::new(0, 40), PacketFormat::new(0, 20));
+        assert_eq!(
+            bridge.packet_formats().map(|(a, b)| (a.ptime_ms, b.ptime_ms)),
+            Some((40, 20))
+        );
+
+        // 5 seconds of 40ms PCMU from leg A
+        let mut output = Vec::new();
+        for i in 0..125u32 {
+            let payload = bytes::Bytes::from(vec![(i % 256) as u8; 320]);
+            let mut packet = RtpPacket::new(0, 1000 + i as u16, 50_000 + i * 320, 0x1234, payload);
+            packet.set_marker(i == 0);
+            output.extend(bridge.adapt(BridgeDirection::AToB, packet));
+        }
+
+        assert_eq!(output.len(), 250);
+        assert!(output[0].marker);
+        for (i, packet) in output.iter().enumerate() {
+            assert_eq!(packet.payload.len(), 160);
+            assert_eq!(packet.payload_type, 0);
+            assert_eq!(packet.ssrc, ssrc_b);
+            assert_eq!(packet.timestamp, 50_000 + i as u32 * 160);
+            assert_eq!(packet.sequence, 1000 + i as u16);
+            assert_eq!(packet.payload[0], (i / 2 % 256) as u8);
+            if i > 0 {
+                assert!(!packet.marker);
+                assert_eq!(packet.timestamp - output[i - 1].timestamp, 160);
+            }
+        }
+
+        // The 20ms leg's packets are forwarded to the 40ms leg re-packetized
+        let mut back = Vec::new();
+        for i in 0..4u32 {
+            let payload = bytes::Bytes::from(vec![0xFFu8; 160]);
+            let packet = RtpPacket::new(0, i as u16, i * 160, 9, payload);
+            back.extend(bridge.adapt(BridgeDirection::BToA, packet));
+        }
+        assert_eq!(back.len(), 2);
+        assert!(back.iter().all(|p| p.payload.len() == 320));
+        assert_eq!(back[1].timestamp - back[0].timestamp, 320);
+    }
+
+    #[tokio::test]
+    async fn test_rtcp_between_muxed_and_non_muxed_legs() {
+        use super::super::port_allocator::RtpPortAllocator;
+        use super::super::rtp::{ReceiverReport, RtcpPacket};
+        use super::super::stream::StreamDirection;
+        use std::net::{IpAddr, Ipv4Addr};
+        use tokio::net::UdpSocket;
+        use tokio::time::{timeout, Duration};
+
+        let localhost = IpAddr::V4(Ipv4Addr::LOCALHOST);
+        let allocator = Arc::new(RtpPortAllocator::new(31200, 31210));
+        let interval = Duration::from_millis(50);
+        let wait = Duration::from_secs(2);
+
+        // Leg A toward a WebRTC client (rtcp-mux), leg B toward a phone
+        // using RTP+1
+        let leg_a = MediaStream::allocate_muxed(allocator.clone(), localhost, 0, 8000)
+            .await
+            .unwrap()
+            .with_rtcp_interval(interval);
+        let leg_b = MediaStream::allocate(allocator.clone(), localhost, 0, 8000)
+            .await
+            .unwrap()
+            .with_rtcp_interval(interval);
+        let (leg_a, leg_b) = (Arc::new(leg_a), Arc::new(leg_b));
+
+        let client = UdpSocket::bind((localhost, 0)).await.unwrap();
+        let phone_rtp = UdpSocket::bind((localhost, 0)).await.unwrap();
+        let phone_rtcp = UdpSocket::bind((localhost, 0)).await.unwrap();
+        let client_addr = client.local_addr().unwrap();
+        leg_a.set_remote(client_addr, client_addr).await;
+        leg_b
+            .set_remote(phone_rtp.local_addr().unwrap(), phone_rtcp.local_addr().unwrap())
+            .await;
+        leg_a.set_direction(StreamDirection::SendRecv).await;
+        leg_b.set_direction(StreamDirection::SendRecv).await;
+        let mut rtcp_a = leg_a.subscribe_rtcp();
+        let mut rtcp_b = leg_b.subscribe_rtcp();
+
+        let bridge = MediaBridge::new(leg_a.clone(), leg_b.clone());
+        assert_eq!(bridge.rtcp_mux(), (true, false));
+        bridge.start().await.unwrap();
+
+        // Our receiver reports reach the client on its single port and the
+        // phone on its RTCP port
+        let mut buf = [0u8; 1500];
+        let (len, _) = timeout(wait, client.recv_from(&mut buf)).await.unwrap().unwrap();
+        match RtcpPacket::parse(&buf[..len]) {
+            Ok(RtcpPacket::ReceiverReport(rr)) => assert_eq!(rr.ssrc, leg_a.ssrc()),
+            other => panic!("Expected a receiver report, got {:?}", other),
+        }
+        let (len, _) = timeout(wait, phone_rtcp.recv_from(&mut buf)).await.unwrap().unwrap();
+        match RtcpPacket::parse(&buf[..len]) {
+            Ok(RtcpPacket::ReceiverReport(rr)) => assert_eq!(rr.ssrc, leg_b.ssrc()),
+            other => panic!("Expected a receiver report, got {:?}", other),
+        }
+
+        // Theirs are received on the RTP port of leg A and RTP+1 of leg B
+        let rtcp_port_b = leg_b.local_rtcp_port().unwrap();
+        assert_eq!(rtcp_port_b, leg_b.local_rtp_port().unwrap() + 1);
+        client
+            .send_to(
+                &ReceiverReport::new(0xC11E).serialize(),
+                (localhost, leg_a.local_rtp_port().unwrap()),
+            )
+            .await
+            .unwrap();
+        phone_rtcp
+            .send_to(&ReceiverReport::new(0xF0E5).serialize(), (localhost, rtcp_port_b))
+            .await
+            .unwrap();
+        match timeout(wait, rtcp_a.recv()).await.unwrap().unwrap() {
+            RtcpPacket::ReceiverReport(rr) => assert_eq!(rr.ssrc, 0xC11E),
+            other => panic!("Expected a receiver report, got {:?}", other),
+        }
+        match timeout(wait, rtcp_b.recv()).await.unwrap().unwrap() {
+            RtcpPacket::ReceiverReport(rr) => assert_eq!(rr.ssrc, 0xF0E5),
+            other => panic!("Expected a receiver report, got {:?}", other),
+        }
+        assert_eq!(leg_a.stats().packets_received, 0);
+
+        bridge.close().await;
+        drop((bridge, leg_a, leg_b));
+        assert_eq!(allocator.in_use(), 0);
+    }
 }
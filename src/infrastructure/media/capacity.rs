@@ -0,0 +1,393 @@
+//! Load feedback for codec negotiation
+//!
+//! [`CapacityMonitor`] counts active calls, estimates their media bandwidth
+//! and counts the concurrent channels of each trunk. When any of them
+//! reaches `constrain_at` of its configured limit, new calls are negotiated
+//! with the constrained profile: codecs are ordered by `constrained_codecs`
+//! (narrowband before G.722 by default) and Opus is set up narrowband at
+//! `constrained_opus_bitrate`. The normal profile returns once every
+//! measure is back below `release_at`. Calls keep the codec they were set
+//! up with.
+
+use super::codec::CodecInfo;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::sync::Mutex;
+use tokio::sync::broadcast;
+use tracing::{info, warn};
+
+/// IP/UDP/RTP headers of one direction at 20ms packets, in kbps
+const PACKET_OVERHEAD_KBPS: u64 = 16;
+
+/// Codec preferences of new calls
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CodecProfile {
+    /// The offerer's order
+    #[default]
+    Normal,
+    /// Low-bandwidth codecs first, lower Opus bitrate
+    Constrained,
+}
+
+/// Limits and the constrained profile
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct CapacityConfig {
+    /// Concurrent calls the system is sized for
+    pub max_calls: Option<usize>,
+    /// Media bandwidth of all calls, both directions, in kbps
+    pub max_bandwidth_kbps: Option<u64>,
+    /// Concurrent channels per trunk (by header rules trunk name)
+    pub trunk_channels: BTreeMap<String, usize>,
+    /// Fraction of a limit at which new calls are constrained
+    pub constrain_at: f64,
+    /// Fraction every measure must drop below to return to normal
+    pub release_at: f64,
+    /// Codec preference order of constrained calls (encoding names)
+    pub constrained_codecs: Vec<String>,
+    /// Opus bitrate of constrained calls, in bits per second
+    pub constrained_opus_bitrate: u32,
+}
+
+impl Default for CapacityConfig {
+    fn default() -> Self {
+        Self {
+            max_calls: None,
+            max_bandwidth_kbps: None,
+            trunk_channels: BTreeMap::new(),
+            constrain_at: 0.8,
+            release_at: 0.7,
+            constrained_codecs: vec![
+                "opus".to_string(),
+                "PCMA".to_string(),
+                "PCMU".to_string(),
+                "G722".to_string(),
+            ],
+            constrained_opus_bitrate: 12000,
+        }
+    }
+}
+
+impl CapacityConfig {
+    pub fn validate(&self) -> Result<(), String> {
+        if !(self.release_at > 0.0 && self.release_at <= self.constrain_at && self.constrain_at <= 1.0) {
+            return Err(format!(
+                "release_at {} and constrain_at {} must satisfy 0 < release_at <= constrain_at <= 1",
+                self.release_at, self.constrain_at
+            ));
+        }
+        if !(6000..=510000).contains(&self.constrained_opus_bitrate) {
+            return Err(format!(
+                "constrained_opus_bitrate {} is outside 6000-510000",
+                self.constrained_opus_bitrate
+            ));
+        }
+        Ok(())
+    }
+
+    /// Position of a codec in the constrained order; unlisted codecs last
+    pub fn constrained_rank(&self, codec: &CodecInfo) -> usize {
+        self.constrained_codecs
+            .iter()
+            .position(|name| name.eq_ignore_ascii_case(&codec.name))
+            .unwrap_or(self.constrained_codecs.len())
+    }
+}
+
+/// Media bandwidth of a call with `codec`, both directions, in kbps
+///
+/// `opus_bitrate` is the Opus target bitrate the call was set up with.
+pub fn estimated_kbps(codec: &CodecInfo, opus_bitrate: Option<u32>) -> u64 {
+    let payload = if codec.name.eq_ignore_ascii_case("opus") {
+        u64::from(opus_bitrate.unwrap_or(24000)).div_ceil(1000)
+    } else {
+        // G.711 and G.722 alike
+        64
+    };
+    2 * (payload + PACKET_OVERHEAD_KBPS)
+}
+
+/// Profile change, published to subscribers
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CapacityEvent {
+    pub profile: CodecProfile,
+    pub previous: CodecProfile,
+    /// Measure that crossed its threshold, e.g. `active calls 80/100`
+    pub reason: String,
+    pub active_calls: usize,
+    pub bandwidth_kbps: u64,
+    pub timestamp: DateTime<Utc>,
+}
+
+/// Concurrent channels of a trunk
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TrunkUsage {
+    pub trunk: String,
+    pub channels: usize,
+    pub limit: Option<usize>,
+}
+
+/// Current load, profile and thresholds
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CapacitySnapshot {
+    pub profile: CodecProfile,
+    pub active_calls: usize,
+    pub bandwidth_kbps: u64,
+    pub trunks: Vec<TrunkUsage>,
+    pub thresholds: CapacityConfig,
+}
+
+#[derive(Debug, Clone)]
+struct ActiveCall {
+    trunk: Option<String>,
+    codec: CodecInfo,
+    kbps: u64,
+}
+
+#[derive(Debug, Default)]
+struct MonitorState {
+    calls: HashMap<String, ActiveCall>,
+    profile: CodecProfile,
+}
+
+impl MonitorState {
+    fn bandwidth_kbps(&self) -> u64 {
+        self.calls.values().map(|c| c.kbps).sum()
+    }
+
+    fn trunk_channels(&self) -> BTreeMap<String, usize> {
+        let mut channels = BTreeMap::new();
+        for trunk in self.calls.values().filter_map(|c| c.trunk.as_ref()) {
+            *channels.entry(trunk.clone()).or_insert(0) += 1;
+        }
+        channels
+    }
+}
+
+/// Tracks load and picks the codec profile of new calls
+pub struct CapacityMonitor {
+    config: CapacityConfig,
+    state: Mutex<MonitorState>,
+    events: broadcast::Sender<CapacityEvent>,
+}
+
+impl CapacityMonitor {
+    pub fn new(config: CapacityConfig) -> Self {
+        let (events, _) = broadcast::channel(64);
+        Self {
+            config,
+            state: Mutex::new(MonitorState::default()),
+            events,
+        }
+    }
+
+    pub fn config(&self) -> &CapacityConfig {
+        &self.config
+    }
+
+    /// Profile new calls are negotiated with
+    pub fn profile(&self) -> CodecProfile {
+        self.state.lock().unwrap().profile
+    }
+
+    /// Profile changes
+    pub fn subscribe(&self) -> broadcast::Receiver<CapacityEvent> {
+        self.events.subscribe()
+    }
+
+    /// A call was set up with `codec`, through `trunk` if it uses one
+    pub fn call_started(
+        &self,
+        call_id: &str,
+        trunk: Option<&str>,
+        codec: &CodecInfo,
+        opus_bitrate: Option<u32>,
+    ) {
+        let mut state = self.state.lock().unwrap();
+        state.calls.insert(
+            call_id.to_string(),
+            ActiveCall {
+                trunk: trunk.map(str::to_string),
+                codec: codec.clone(),
+                kbps: estimated_kbps(codec, opus_bitrate),
+            },
+        );
+        self.evaluate(&mut state);
+    }
+
+    pub fn call_ended(&self, call_id: &str) {
+        let mut state = self.state.lock().unwrap();
+        if state.calls.remove(call_id).is_some() {
+            self.evaluate(&mut state);
+        }
+    }
+
+    /// Codec an active call was set up with
+    pub fn call_codec(&self, call_id: &str) -> Option<CodecInfo> {
+        self.state
+            .lock()
+            .unwrap()
+            .calls
+            .get(call_id)
+            .map(|c| c.codec.clone())
+    }
+
+    pub fn snapshot(&self) -> CapacitySnapshot {
+        let state = self.state.lock().unwrap();
+        let channels = state.trunk_channels();
+        let mut trunks: BTreeMap<String, TrunkUsage> = self
+            .config
+            .trunk_channels
+            .iter()
+            .map(|(trunk, limit)| {
+                (
+                    trunk.clone(),
+                    TrunkUsage {
+                        trunk: trunk.clone(),
+                        channels: 0,
+                        limit: Some(*limit),
+                    },
+                )
+            })
+            .collect();
+        for (trunk, count) in channels {
+            trunks
+                .entry(trunk.clone())
+                .or_insert(TrunkUsage {
+                    trunk,
+                    channels: 0,
+                    limit: None,
+                })
+                .channels = count;
+        }
+
+        CapacitySnapshot {
+            profile: state.profile,
+            active_calls: state.calls.len(),
+            bandwidth_kbps: state.bandwidth_kbps(),
+            trunks: trunks.into_values().collect(),
+            thresholds: self.config.clone(),
+        }
+    }
+
+    /// The most loaded measure as (fraction of its limit, description)
+    fn peak_load(&self, state: &MonitorState) -> Option<(f64, String)> {
+        let mut loads = Vec::new();
+        if let Some(max) = self.config.max_calls.filter(|m| *m > 0) {
+            let calls = state.calls.len();
+            loads.push((calls as f64 / max as f64, format!("active calls {}/{}", calls, max)));
+        }
+        if let Some(max) = self.config.max_bandwidth_kbps.filter(|m| *m > 0) {
+            let kbps = state.bandwidth_kbps();
+            loads.push((
+                kbps as f64 / max as f64,
+                format!("media bandwidth {}/{} kbps", kbps, max),
+            ));
+        }
+        let channels = state.trunk_channels();
+        for (trunk, limit) in self.config.trunk_channels.iter().filter(|(_, l)| **l > 0) {
+            let used = channels.get(trunk).copied().unwrap_or(0);
+            loads.push((
+                used as f64 / *limit as f64,
+                format!("trunk {} channels {}/{}", trunk, used, limit),
+            ));
+        }
+        loads.into_iter().max_by(|a, b| a.0.total_cmp(&b.0))
+    }
+
+    fn evaluate(&self, state: &mut MonitorState) {
+        let Some((load, reason)) = self.peak_load(state) else {
+            return;
+        };
+        let profile = match state.profile {
+            CodecProfile::Normal if load >= self.config.constrain_at => CodecProfile::Constrained,
+            CodecProfile::Constrained if load < self.config.release_at => CodecProfile::Normal,
+            current => current,
+        };
+        if profile == state.profile {
+            return;
+        }
+
+        let event = CapacityEvent {
+            profile,
+            previous: state.profile,
+            reason,
+            active_calls: state.calls.len(),
+            bandwidth_kbps: state.bandwidth_kbps(),
+            timestamp: Utc::now(),
+        };
+        state.profile = profile;
+        match profile {
+            CodecProfile::Constrained => warn!("New calls use constrained codecs: {}", event.reason),
+            CodecProfile::Normal => info!("New calls use normal codecs again: {}", event.reason),
+        }
+        let _ = self.events.send(event);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pcma() -> CodecInfo {
+        CodecInfo::new(8, "PCMA".to_string(), 8000)
+    }
+
+    #[test]
+    fn test_thresholds_with_hysteresis() {
+        let mut trunk_channels = BTreeMap::new();
+        trunk_channels.insert("carrier".to_string(), 4);
+        let monitor = CapacityMonitor::new(CapacityConfig {
+            max_calls: Some(20),
+            trunk_channels,
+            ..Default::default()
+        });
+        let mut events = monitor.subscribe();
+
+        for i in 0..7 {
+            monitor.call_started(&format!("call-{}", i), None, &pcma(), None);
+        }
+        // 3 of 4 trunk channels is below 80%; the fourth is not
+        for i in 0..4 {
+            assert_eq!(monitor.profile(), CodecProfile::Normal);
+            monitor.call_started(&format!("trunk-{}", i), Some("carrier"), &pcma(), None);
+        }
+        assert_eq!(monitor.profile(), CodecProfile::Constrained);
+        let event = events.try_recv().unwrap();
+        assert_eq!(event.previous, CodecProfile::Normal);
+        assert_eq!(event.reason, "trunk carrier channels 4/4");
+        assert_eq!(event.active_calls, 11);
+
+        // Between release_at and constrain_at the profile stays
+        monitor.call_ended("trunk-3");
+        assert_eq!(monitor.profile(), CodecProfile::Constrained);
+        monitor.call_ended("trunk-2");
+        assert_eq!(monitor.profile(), CodecProfile::Normal);
+        assert_eq!(events.try_recv().unwrap().profile, CodecProfile::Normal);
+
+        let snapshot = monitor.snapshot();
+        assert_eq!(snapshot.active_calls, 9);
+        assert_eq!(snapshot.bandwidth_kbps, 9 * 160);
+        assert_eq!(snapshot.trunks[0].channels, 2);
+        assert_eq!(snapshot.trunks[0].limit, Some(4));
+    }
+
+    #[test]
+    fn test_bandwidth_estimate() {
+        let opus = CodecInfo::new(111, "opus".to_string(), 48000);
+        assert_eq!(estimated_kbps(&pcma(), None), 160);
+        assert_eq!(estimated_kbps(&opus, Some(12000)), 56);
+
+        let monitor = CapacityMonitor::new(CapacityConfig {
+            max_bandwidth_kbps: Some(400),
+            ..Default::default()
+        });
+        monitor.call_started("a", None, &opus, Some(24000));
+        monitor.call_started("b", None, &pcma(), None);
+        assert_eq!(monitor.profile(), CodecProfile::Normal);
+        monitor.call_started("c", None, &opus, Some(24000));
+        assert_eq!(monitor.profile(), CodecProfile::Constrained);
+    }
+}
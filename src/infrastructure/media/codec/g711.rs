@@ -37,6 +37,31 @@ impl G711Type {
             G711Type::PCMA => "PCMA",
         }
     }
+
+    /// G.711 type of an RTP payload type
+    pub fn from_payload_type(payload_type: u8) -> Option<Self> {
+        match payload_type {
+            0 => Some(G711Type::PCMU),
+            8 => Some(G711Type::PCMA),
+            _ => None,
+        }
+    }
+
+    /// Encode PCM samples
+    pub fn encode(&self, pcm: &[i16]) -> Bytes {
+        match self {
+            G711Type::PCMU => PcmuCodec::encode(pcm),
+            G711Type::PCMA => PcmaCodec::encode(pcm),
+        }
+    }
+
+    /// Decode to PCM samples
+    pub fn decode(&self, data: &[u8]) -> Vec<i16> {
+        match self {
+            G711Type::PCMU => PcmuCodec::decode(data),
+            G711Type::PCMA => PcmaCodec::decode(data),
+        }
+    }
 }
 
 /// G.711 μ-law (PCMU) Codec
@@ -168,9 +168,9 @@ impl G722Encoder {
                 break;
             }
             // Placeholder: Simple downsampling without proper filtering
-            let lower = self.encode_subband(&chunk[0], &mut self.band1);
+            let lower = self.encode_subband(&chunk[0], &self.band1);
             let upper = if chunk.len() > 1 {
-                self.encode_subband(&chunk[1], &mut self.band2)
+                self.encode_subband(&chunk[1], &self.band2)
             } else {
                 0
             };
@@ -262,9 +262,9 @@ impl G722Decoder {
             };
 
             let idx = i * 2;
-            output[idx] = self.decode_subband(lower, &mut self.band1);
+            output[idx] = self.decode_subband(lower, &self.band1);
             if idx + 1 < output.len() {
-                output[idx + 1] = self.decode_subband(upper, &mut self.band2);
+                output[idx + 1] = self.decode_subband(upper, &self.band2);
             }
         }
 
@@ -7,5 +7,5 @@ pub mod opus;
 
 pub use g711::{G711Type, PcmaCodec, PcmuCodec};
 pub use g722::{G722Config, G722Decoder, G722Encoder};
-pub use negotiator::{CodecInfo, CodecNegotiator};
+pub use negotiator::{CodecInfo, CodecNegotiator, PayloadMap};
 pub use opus::{OpusApplication, OpusConfig, OpusDecoder, OpusEncoder};
@@ -3,6 +3,10 @@
 //! Handles SDP codec negotiation between endpoints
 
 use super::g711::G711Type;
+use super::opus::OpusConfig;
+use crate::infrastructure::media::capacity::{CapacityMonitor, CodecProfile};
+use crate::infrastructure::media::ptime::DEFAULT_PTIME_MS;
+use std::sync::Arc;
 
 /// Codec Information
 #[derive(Debug, Clone, PartialEq)]
@@ -29,9 +33,72 @@ impl CodecInfo {
     }
 }
 
+/// Payload types negotiated for a call, numbered as in the offer
+///
+/// Dynamic payload types are chosen by the offerer (telephone-event may be
+/// 96 on one phone and 127 on the next), so the media layer looks codecs up
+/// here instead of assuming our own numbering.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct PayloadMap {
+    /// Accepted codecs in the offer's order, with the offer's payload types
+    pub codecs: Vec<CodecInfo>,
+    /// Payload type of RFC 2833 telephone-events, if offered
+    pub telephone_event: Option<u8>,
+    /// Encoder settings of the call, when Opus was accepted
+    pub opus: Option<OpusConfig>,
+}
+
+impl PayloadMap {
+    /// Codec media is sent with (the first accepted one)
+    pub fn primary(&self) -> Option<&CodecInfo> {
+        self.codecs.first()
+    }
+
+    /// Codec carried with `payload_type`
+    pub fn codec(&self, payload_type: u8) -> Option<&CodecInfo> {
+        self.codecs.iter().find(|c| c.payload_type == payload_type)
+    }
+
+    /// Accepted payload types, telephone-event last
+    pub fn payload_types(&self) -> Vec<u8> {
+        self.codecs
+            .iter()
+            .map(|c| c.payload_type)
+            .chain(self.telephone_event)
+            .collect()
+    }
+
+    pub fn is_telephone_event(&self, payload_type: u8) -> bool {
+        self.telephone_event == Some(payload_type)
+    }
+
+    /// Put the codec a call already uses first again, so renegotiating it
+    /// under a different profile does not switch codecs
+    pub fn keep_primary(&mut self, codec: &CodecInfo) {
+        let current = self.codecs.iter().position(|c| {
+            c.name.eq_ignore_ascii_case(&codec.name) && c.clock_rate == codec.clock_rate
+        });
+        if let Some(index) = current {
+            let kept = self.codecs.remove(index);
+            self.codecs.insert(0, kept);
+        }
+    }
+}
+
+/// Lowest dynamically assigned payload type (RFC 3551)
+const FIRST_DYNAMIC_PT: u8 = 96;
+
+/// Packet times accepted from a remote by default, in milliseconds
+const MIN_PTIME_MS: u32 = 10;
+const MAX_PTIME_MS: u32 = 60;
+
 /// Codec Negotiator
 pub struct CodecNegotiator {
     supported_codecs: Vec<CodecInfo>,
+    /// Packet times accepted from a remote (min, max), in milliseconds
+    ptime_bounds: (u32, u32),
+    /// Load feedback choosing the codec profile of new calls
+    capacity: Option<Arc<CapacityMonitor>>,
 }
 
 impl CodecNegotiator {
@@ -52,7 +119,11 @@ impl CodecNegotiator {
             CodecInfo::new(8, "PCMA".to_string(), 8000),
         ];
 
-        Self { supported_codecs }
+        Self {
+            supported_codecs,
+            ptime_bounds: (MIN_PTIME_MS, MAX_PTIME_MS),
+            capacity: None,
+        }
     }
 
     /// Create negotiator for VoIP (prioritizes quality and efficiency)
@@ -64,7 +135,11 @@ impl CodecNegotiator {
             CodecInfo::new(8, "PCMA".to_string(), 8000),
         ];
 
-        Self { supported_codecs }
+        Self {
+            supported_codecs,
+            ptime_bounds: (MIN_PTIME_MS, MAX_PTIME_MS),
+            capacity: None,
+        }
     }
 
     /// Create negotiator for WebRTC (Opus-first)
@@ -80,7 +155,68 @@ impl CodecNegotiator {
             CodecInfo::new(8, "PCMA".to_string(), 8000),
         ];
 
-        Self { supported_codecs }
+        Self {
+            supported_codecs,
+            ptime_bounds: (MIN_PTIME_MS, MAX_PTIME_MS),
+            capacity: None,
+        }
+    }
+
+    /// Packet times accepted from a remote, in milliseconds
+    pub fn with_ptime_bounds(mut self, min_ms: u32, max_ms: u32) -> Self {
+        self.ptime_bounds = (min_ms.min(max_ms), max_ms.max(min_ms));
+        self
+    }
+
+    /// Negotiate with the constrained profile while `monitor` says so
+    pub fn with_capacity_monitor(mut self, monitor: Arc<CapacityMonitor>) -> Self {
+        self.capacity = Some(monitor);
+        self
+    }
+
+    pub fn capacity_monitor(&self) -> Option<&Arc<CapacityMonitor>> {
+        self.capacity.as_ref()
+    }
+
+    /// Profile new calls are negotiated with
+    pub fn profile(&self) -> CodecProfile {
+        self.capacity
+            .as_ref()
+            .map(|monitor| monitor.profile())
+            .unwrap_or_default()
+    }
+
+    /// Opus encoder settings of a new call
+    ///
+    /// Constrained calls run narrowband at the configured lower bitrate.
+    pub fn opus_config(&self) -> OpusConfig {
+        self.opus_config_for(self.profile())
+    }
+
+    fn opus_config_for(&self, profile: CodecProfile) -> OpusConfig {
+        let mut config = OpusConfig::voip();
+        if let (CodecProfile::Constrained, Some(monitor)) = (profile, &self.capacity) {
+            config.sample_rate = 8000;
+            config.bitrate = config.bitrate.min(monitor.config().constrained_opus_bitrate);
+        }
+        config
+    }
+
+    /// Packet time we offer: 20ms, unless outside the bounds
+    pub fn offer_ptime(&self) -> u32 {
+        DEFAULT_PTIME_MS.clamp(self.ptime_bounds.0, self.ptime_bounds.1)
+    }
+
+    /// Packet time to use with a remote that sent `ptime` and `max_ptime`
+    ///
+    /// The remote's packet time is kept when within our bounds (and its own
+    /// a=maxptime), otherwise the nearest one that is.
+    pub fn accept_ptime(&self, ptime: Option<u32>, max_ptime: Option<u32>) -> u32 {
+        let (min, mut max) = self.ptime_bounds;
+        if let Some(remote_max) = max_ptime {
+            max = max.min(remote_max).max(min);
+        }
+        ptime.unwrap_or(DEFAULT_PTIME_MS).clamp(min, max)
     }
 
     /// Add custom codec to supported list
@@ -99,6 +235,61 @@ impl CodecNegotiator {
             .collect()
     }
 
+    /// Negotiate the codecs of an offered m-line
+    ///
+    /// `rtpmap` holds the offer's (payload type, encoding) pairs. Codecs
+    /// are matched by encoding name and clock rate and keep the payload
+    /// type the offer gave them; static payload types without an rtpmap
+    /// line are matched by number. Under the constrained profile the
+    /// accepted codecs are reordered by the configured constrained order.
+    pub fn negotiate_offer(&self, formats: &[u8], rtpmap: &[(u8, String)]) -> PayloadMap {
+        let mut payloads = PayloadMap::default();
+        for &pt in formats {
+            let encoding = rtpmap.iter().find(|(p, _)| *p == pt).map(|(_, e)| e.as_str());
+            let Some(encoding) = encoding else {
+                if pt < FIRST_DYNAMIC_PT {
+                    if let Some(codec) = self.find_codec(pt) {
+                        payloads.codecs.push(codec.clone());
+                    }
+                }
+                continue;
+            };
+
+            let mut parts = encoding.split('/');
+            let name = parts.next().unwrap_or_default();
+            let clock_rate: u32 = parts.next().and_then(|r| r.trim().parse().ok()).unwrap_or(0);
+            if name.eq_ignore_ascii_case("telephone-event") {
+                if payloads.telephone_event.is_none() && clock_rate == 8000 {
+                    payloads.telephone_event = Some(pt);
+                }
+                continue;
+            }
+
+            let supported = self
+                .supported_codecs
+                .iter()
+                .find(|c| c.name.eq_ignore_ascii_case(name) && c.clock_rate == clock_rate);
+            if let Some(codec) = supported {
+                if payloads.codec(pt).is_none() {
+                    payloads.codecs.push(CodecInfo {
+                        payload_type: pt,
+                        ..codec.clone()
+                    });
+                }
+            }
+        }
+
+        let profile = self.profile();
+        if let (CodecProfile::Constrained, Some(monitor)) = (profile, &self.capacity) {
+            let config = monitor.config();
+            payloads.codecs.sort_by_key(|codec| config.constrained_rank(codec));
+        }
+        if payloads.codecs.iter().any(|c| c.name.eq_ignore_ascii_case("opus")) {
+            payloads.opus = Some(self.opus_config_for(profile));
+        }
+        payloads
+    }
+
     /// Find codec by payload type
     pub fn find_codec(&self, payload_type: u8) -> Option<&CodecInfo> {
         self.supported_codecs
@@ -229,6 +420,29 @@ mod tests {
         assert_eq!(best.payload_type, 8); // Should select first offered
     }
 
+    #[test]
+    fn test_negotiate_offer_keeps_offered_payload_types() {
+        let negotiator = CodecNegotiator::new();
+        let rtpmap = vec![
+            (98, "OPUS/48000/2".to_string()),
+            (97, "iLBC/8000".to_string()),
+            (96, "telephone-event/8000".to_string()),
+        ];
+
+        let payloads = negotiator.negotiate_offer(&[98, 97, 8, 0, 96], &rtpmap);
+        assert_eq!(payloads.payload_types(), vec![98, 8, 0, 96]);
+        assert_eq!(payloads.primary().unwrap().name, "opus");
+        assert_eq!(payloads.codec(98).unwrap().channels, 2);
+        assert_eq!(payloads.codec(8).unwrap().name, "PCMA");
+        assert!(payloads.is_telephone_event(96));
+        assert!(payloads.codec(111).is_none());
+
+        // Unknown dynamic payload types are never matched by number
+        let payloads = negotiator.negotiate_offer(&[111, 0], &[]);
+        assert_eq!(payloads.payload_types(), vec![0]);
+        assert_eq!(payloads.telephone_event, None);
+    }
+
     #[test]
     fn test_g711_type_mapping() {
         let negotiator = CodecNegotiator::new();
@@ -237,4 +451,58 @@ mod tests {
         assert_eq!(negotiator.g711_type(8), Some(G711Type::PCMA));
         assert_eq!(negotiator.g711_type(97), None);
     }
+
+    #[test]
+    fn test_ptime_negotiation() {
+        let negotiator = CodecNegotiator::new();
+        assert_eq!(negotiator.offer_ptime(), 20);
+        assert_eq!(negotiator.accept_ptime(None, None), 20);
+        assert_eq!(negotiator.accept_ptime(Some(40), None), 40);
+        assert_eq!(negotiator.accept_ptime(Some(40), Some(30)), 30);
+        assert_eq!(negotiator.accept_ptime(Some(120), None), 60);
+
+        let negotiator = CodecNegotiator::new().with_ptime_bounds(30, 40);
+        assert_eq!(negotiator.offer_ptime(), 30);
+        assert_eq!(negotiator.accept_ptime(Some(20), None), 30);
+    }
+
+    #[test]
+    fn test_constrained_profile_under_load() {
+        use crate::infrastructure::media::capacity::CapacityConfig;
+
+        let monitor = Arc::new(CapacityMonitor::new(CapacityConfig {
+            max_calls: Some(5),
+            ..Default::default()
+        }));
+        let negotiator = CodecNegotiator::new().with_capacity_monitor(monitor.clone());
+        let rtpmap = vec![(9, "G722/8000".to_string()), (8, "PCMA/8000".to_string())];
+
+        let first = negotiator.negotiate_offer(&[9, 8], &rtpmap);
+        assert_eq!(first.primary().unwrap().name, "G722");
+        monitor.call_started("call-0", None, first.primary().unwrap(), None);
+
+        let opus = negotiator.negotiate_offer(&[111, 8], &[(111, "opus/48000/2".to_string())]);
+        assert_eq!(opus.opus.unwrap().bitrate, 24000);
+
+        let pcma = CodecInfo::new(8, "PCMA".to_string(), 8000);
+        for i in 1..4 {
+            monitor.call_started(&format!("call-{}", i), Some("carrier"), &pcma, None);
+        }
+        assert_eq!(negotiator.profile(), CodecProfile::Constrained);
+
+        let constrained = negotiator.negotiate_offer(&[9, 8], &rtpmap);
+        assert_eq!(constrained.payload_types(), vec![8, 9]);
+        let opus = negotiator.negotiate_offer(&[111, 8], &[(111, "opus/48000/2".to_string())]);
+        let config = opus.opus.unwrap();
+        assert_eq!(config.bitrate, 12000);
+        assert_eq!(config.sample_rate, 8000);
+        assert!(config.validate().is_ok());
+
+        // The call set up before the switch keeps its codec
+        assert_eq!(first.primary().unwrap().name, "G722");
+        assert_eq!(monitor.call_codec("call-0").unwrap().name, "G722");
+        let mut renegotiated = negotiator.negotiate_offer(&[9, 8], &rtpmap);
+        renegotiated.keep_primary(&monitor.call_codec("call-0").unwrap());
+        assert_eq!(renegotiated.payload_types(), vec![9, 8]);
+    }
 }
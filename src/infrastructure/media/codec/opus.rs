@@ -338,6 +338,105 @@ impl<'a> OpusPacket<'a> {
 
         (frame_size * frames * sample_rate as usize) / 48000
     }
+
+    /// Whether frames are coded in SILK or hybrid mode, the modes that
+    /// carry in-band FEC of the previous frame
+    pub fn uses_silk(&self) -> bool {
+        self.config() < 16
+    }
+
+    /// Samples per frame at 48 kHz
+    fn frame_samples(&self) -> usize {
+        let config = self.config() as usize;
+        match config {
+            0..=11 => [480, 960, 1920, 2880][config % 4],
+            12..=15 => [480, 960][config % 2],
+            _ => [120, 240, 480, 960][config % 4],
+        }
+    }
+
+    /// Check the framing a decoder requires before decoding
+    /// (RFC 6716 section 3.4, R1 to R7)
+    ///
+    /// Corrupted payloads almost always break one of these rules; an error
+    /// here means the packet cannot be decoded.
+    pub fn validate(&self) -> Result<(), String> {
+        const MAX_FRAME_BYTES: usize = 1275;
+        const MAX_PACKET_SAMPLES: usize = 5760; // 120 ms at 48 kHz
+
+        let payload = &self.data[1..];
+        match self.frame_count() {
+            0 => {
+                if payload.len() > MAX_FRAME_BYTES {
+                    return Err("Opus frame too long".to_string());
+                }
+            }
+            1 => {
+                if !payload.len().is_multiple_of(2) || payload.len() / 2 > MAX_FRAME_BYTES {
+                    return Err("Opus frames of unequal size in code 1 packet".to_string());
+                }
+            }
+            2 => {
+                let (first, used) = frame_length(payload)?;
+                let rest = payload.len() - used;
+                if first > rest || rest - first > MAX_FRAME_BYTES {
+                    return Err("Opus frame lengths exceed code 2 packet".to_string());
+                }
+            }
+            _ => {
+                let Some(&header) = payload.first() else {
+                    return Err("Opus code 3 packet without frame count".to_string());
+                };
+                let frames = (header & 0x3F) as usize;
+                if frames == 0 || frames * self.frame_samples() > MAX_PACKET_SAMPLES {
+                    return Err(format!("Opus code 3 packet with {} frames", frames));
+                }
+                let mut offset = 1;
+                let mut padding = 0;
+                if header & 0x40 != 0 {
+                    loop {
+                        let Some(&byte) = payload.get(offset) else {
+                            return Err("Opus padding length truncated".to_string());
+                        };
+                        offset += 1;
+                        padding += if byte == 255 { 254 } else { byte as usize };
+                        if byte != 255 {
+                            break;
+                        }
+                    }
+                }
+                let Some(mut rest) = payload.len().checked_sub(offset + padding) else {
+                    return Err("Opus padding exceeds packet".to_string());
+                };
+                if header & 0x80 == 0 {
+                    if rest % frames != 0 || rest / frames > MAX_FRAME_BYTES {
+                        return Err("Opus CBR frames of unequal size".to_string());
+                    }
+                } else {
+                    for _ in 1..frames {
+                        let (length, used) = frame_length(&payload[offset..])?;
+                        offset += used;
+                        rest = rest
+                            .checked_sub(used + length)
+                            .ok_or_else(|| "Opus frame lengths exceed packet".to_string())?;
+                    }
+                    if rest > MAX_FRAME_BYTES {
+                        return Err("Opus frame too long".to_string());
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Frame length coded in one or two bytes, and the bytes it took
+fn frame_length(data: &[u8]) -> Result<(usize, usize), String> {
+    match data {
+        [first, ..] if *first < 252 => Ok((*first as usize, 1)),
+        [first, second, ..] => Ok((*second as usize * 4 + *first as usize, 2)),
+        _ => Err("Opus frame length truncated".to_string()),
+    }
 }
 
 /// Opus bandwidth
@@ -457,6 +556,29 @@ mod tests {
         assert_eq!(packet.config(), 15); // (0x78 >> 3) & 0x1F
     }
 
+    #[test]
+    fn test_opus_packet_validate() {
+        let valid = |data: &[u8]| OpusPacket::parse(data).unwrap().validate().is_ok();
+
+        // Code 0, 1 and 2 packets of 20 ms SILK frames
+        assert!(valid(&[0x08, 1, 2, 3]));
+        assert!(valid(&[0x09, 1, 2, 3, 4]));
+        assert!(!valid(&[0x09, 1, 2, 3]));
+        assert!(valid(&[0x0A, 2, 1, 2, 3]));
+        assert!(!valid(&[0x0A, 9, 1, 2]));
+        assert!(!valid(&[0x0A, 253]));
+
+        // Code 3: two CBR frames, then VBR with padding
+        assert!(valid(&[0x0B, 0x02, 1, 2, 3, 4]));
+        assert!(!valid(&[0x0B, 0x02, 1, 2, 3]));
+        assert!(valid(&[0x0B, 0xC2, 1, 2, 7, 7, 7, 0, 0]));
+        assert!(!valid(&[0x0B, 0xC2, 9, 2, 7, 7]));
+        // 60 ms frames: three are too long, as is no frame at all
+        assert!(!valid(&[0x1B, 0x03, 1, 2, 3]));
+        assert!(!valid(&[0x0B, 0x00]));
+        assert!(OpusPacket::parse(&[0x08]).unwrap().uses_silk());
+    }
+
     #[test]
     fn test_opus_config_setters() {
         let config = OpusConfig::default();
@@ -0,0 +1,202 @@
+//! Decode health of received Opus media
+//!
+//! A leg whose Opus packets arrive corrupted (a broken encoder, a middlebox
+//! mangling payloads) leaves the other party listening to concealment
+//! noise. [`DecodeHealth`] checks each packet's framing as the decoder
+//! would and tracks the share of packets lost to decode errors over a
+//! sliding window. A corrupted packet followed by an intact SILK or hybrid
+//! one is recovered from that packet's in-band FEC when FEC was negotiated;
+//! anything else is concealed (PLC) and counts as an error.
+
+use super::codec::opus::OpusPacket;
+use metrics::counter;
+use serde::Serialize;
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+
+/// Sliding window the error rate is taken over by default
+pub const DEFAULT_DECODE_WINDOW: Duration = Duration::from_secs(2);
+
+/// Packets in the window before an error rate is reported
+const MIN_PACKETS: usize = 10;
+
+/// What became of a received packet
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DecodeOutcome {
+    Decoded,
+    /// Undecodable, rebuilt from the next packet's FEC
+    Recovered,
+    /// Undecodable and concealed
+    Concealed,
+}
+
+/// Packets by outcome since the stream started
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+pub struct DecodeCounters {
+    pub decoded: u64,
+    pub recovered: u64,
+    pub concealed: u64,
+}
+
+/// Decode error tracking of one stream
+#[derive(Debug)]
+pub struct DecodeHealth {
+    window: Duration,
+    outcomes: VecDeque<(Instant, DecodeOutcome)>,
+    /// Sequence number of an undecodable packet awaiting the next one's FEC
+    awaiting_fec: Option<u16>,
+    counters: DecodeCounters,
+}
+
+impl Default for DecodeHealth {
+    fn default() -> Self {
+        Self::new(DEFAULT_DECODE_WINDOW)
+    }
+}
+
+impl DecodeHealth {
+    pub fn new(window: Duration) -> Self {
+        Self {
+            window,
+            outcomes: VecDeque::new(),
+            awaiting_fec: None,
+            counters: DecodeCounters::default(),
+        }
+    }
+
+    /// Account for an Opus packet received at `now`; whether it decodes
+    ///
+    /// `fec` is whether in-band FEC was negotiated.
+    pub fn observe(&mut self, now: Instant, sequence: u16, payload: &[u8], fec: bool) -> bool {
+        let packet = OpusPacket::parse(payload).and_then(|p| p.validate().map(|_| p));
+        if let Some(lost) = self.awaiting_fec.take() {
+            let recovered = match &packet {
+                Ok(packet) => packet.uses_silk() && sequence == lost.wrapping_add(1),
+                Err(_) => false,
+            };
+            let outcome = if recovered {
+                DecodeOutcome::Recovered
+            } else {
+                DecodeOutcome::Concealed
+            };
+            self.record(now, outcome);
+        }
+
+        match packet {
+            Ok(_) => {
+                self.record(now, DecodeOutcome::Decoded);
+                true
+            }
+            Err(_) if fec => {
+                self.awaiting_fec = Some(sequence);
+                false
+            }
+            Err(_) => {
+                self.record(now, DecodeOutcome::Concealed);
+                false
+            }
+        }
+    }
+
+    fn record(&mut self, now: Instant, outcome: DecodeOutcome) {
+        let name = match outcome {
+            DecodeOutcome::Decoded => {
+                self.counters.decoded += 1;
+                "decoded"
+            }
+            DecodeOutcome::Recovered => {
+                self.counters.recovered += 1;
+                "recovered"
+            }
+            DecodeOutcome::Concealed => {
+                self.counters.concealed += 1;
+                "concealed"
+            }
+        };
+        counter!("media_opus_decode_packets_total", "outcome" => name).increment(1);
+        self.outcomes.push_back((now, outcome));
+        self.expire(now);
+    }
+
+    fn expire(&mut self, now: Instant) {
+        while let Some((at, _)) = self.outcomes.front() {
+            if now.saturating_duration_since(*at) <= self.window {
+                break;
+            }
+            self.outcomes.pop_front();
+        }
+    }
+
+    /// Share of the packets of the last window that were concealed; zero
+    /// until the window holds enough packets to tell
+    pub fn error_rate(&mut self, now: Instant) -> f64 {
+        self.expire(now);
+        if self.outcomes.len() < MIN_PACKETS {
+            return 0.0;
+        }
+        let concealed = self
+            .outcomes
+            .iter()
+            .filter(|(_, outcome)| *outcome == DecodeOutcome::Concealed)
+            .count();
+        concealed as f64 / self.outcomes.len() as f64
+    }
+
+    pub fn counters(&self) -> DecodeCounters {
+        self.counters
+    }
+
+    /// Forget the window, e.g. after a codec change; counters are kept
+    pub fn reset(&mut self) {
+        self.outcomes.clear();
+        self.awaiting_fec = None;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const GOOD: &[u8] = &[0x08, 0x55, 0x66, 0x77];
+    const CORRUPT: &[u8] = &[0x0B, 0x00];
+
+    #[test]
+    fn test_fec_recovers_isolated_errors_only() {
+        let start = Instant::now();
+        let mut health = DecodeHealth::default();
+        for sequence in 0..20u16 {
+            let payload = if sequence % 4 == 1 { CORRUPT } else { GOOD };
+            health.observe(start, sequence, payload, true);
+        }
+        assert_eq!(health.error_rate(start), 0.0);
+        assert_eq!(health.counters().recovered, 5);
+
+        // Back to back errors leave nothing to recover from
+        for sequence in 20..40u16 {
+            health.observe(start, sequence, CORRUPT, true);
+        }
+        assert!(health.error_rate(start) > 0.4);
+        assert_eq!(health.counters().concealed, 19);
+
+        // The window moves on
+        let later = start + Duration::from_secs(3);
+        for sequence in 40..60u16 {
+            health.observe(later, sequence, GOOD, true);
+        }
+        assert!(health.error_rate(later) < 0.1);
+    }
+
+    #[test]
+    fn test_errors_concealed_without_fec() {
+        let start = Instant::now();
+        let mut health = DecodeHealth::default();
+        for sequence in 0..10u16 {
+            let payload = if sequence % 2 == 0 { CORRUPT } else { GOOD };
+            assert_eq!(health.observe(start, sequence, payload, false), sequence % 2 == 1);
+        }
+        assert_eq!(health.error_rate(start), 0.5);
+        health.reset();
+        assert_eq!(health.error_rate(start), 0.0);
+        assert_eq!(health.counters().concealed, 5);
+    }
+}
@@ -0,0 +1,233 @@
+//! Symmetric RTP latching
+//!
+//! Phones behind NAT advertise their private address in SDP, where our
+//! media never arrives. A latching stream sends instead to the address the
+//! phone's media comes from, once a few packets from it agree on SSRC and a
+//! negotiated payload type (a single probe from a scanner is not enough).
+//! A source that keeps sending from elsewhere, as after a mobile handover,
+//! is latched onto in turn.
+
+use super::rtp::RtpPacket;
+use std::net::{IpAddr, SocketAddr};
+
+/// Packets a new source needs by default before it is latched onto
+pub const DEFAULT_CONFIRM_PACKETS: u32 = 3;
+
+/// Packets by default before moving to a source sending another SSRC (half
+/// a second of 20 ms packets)
+pub const DEFAULT_RELATCH_PACKETS: u32 = 25;
+
+/// When a stream latches onto the address its media comes from
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LatchConfig {
+    pub enabled: bool,
+    /// Consecutive packets from a source, with one SSRC and a negotiated
+    /// payload type, before latching onto it; also before moving to a
+    /// source continuing the latched SSRC
+    pub confirm_packets: u32,
+    /// Consecutive packets before moving to a source with another SSRC
+    pub relatch_packets: u32,
+}
+
+impl Default for LatchConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            confirm_packets: DEFAULT_CONFIRM_PACKETS,
+            relatch_packets: DEFAULT_RELATCH_PACKETS,
+        }
+    }
+}
+
+impl LatchConfig {
+    pub fn enabled() -> Self {
+        Self {
+            enabled: true,
+            ..Self::default()
+        }
+    }
+
+    /// Latching for a leg advertising `sdp_ip` whose signaling came from
+    /// `signaling_ip`: on when the SDP address is private but the signaling
+    /// one is public, which is a phone behind NAT
+    pub fn for_leg(sdp_ip: IpAddr, signaling_ip: Option<IpAddr>) -> Self {
+        let natted = is_private(sdp_ip) && signaling_ip.is_some_and(is_public);
+        Self {
+            enabled: natted,
+            ..Self::default()
+        }
+    }
+}
+
+/// RFC 1918 address, or an IPv6 unique local one
+pub fn is_private(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(v4) => v4.is_private(),
+        IpAddr::V6(v6) => (v6.segments()[0] & 0xfe00) == 0xfc00,
+    }
+}
+
+/// Address routable on the internet, as far as one can tell locally
+pub fn is_public(ip: IpAddr) -> bool {
+    if ip.is_unspecified() || ip.is_loopback() || ip.is_multicast() || is_private(ip) {
+        return false;
+    }
+    match ip {
+        IpAddr::V4(v4) => !v4.is_link_local() && !v4.is_broadcast(),
+        IpAddr::V6(v6) => (v6.segments()[0] & 0xffc0) != 0xfe80,
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Source {
+    addr: SocketAddr,
+    ssrc: u32,
+}
+
+/// Latching state of a stream
+#[derive(Debug, Default)]
+pub struct RtpLatch {
+    config: LatchConfig,
+    latched: Option<Source>,
+    /// Source being confirmed and its consecutive packets
+    candidate: Option<(Source, u32)>,
+}
+
+impl RtpLatch {
+    pub fn new(config: LatchConfig) -> Self {
+        Self {
+            config,
+            ..Self::default()
+        }
+    }
+
+    pub fn config(&self) -> LatchConfig {
+        self.config
+    }
+
+    /// Change the configuration; what was learned is kept
+    pub fn set_config(&mut self, config: LatchConfig) {
+        self.config = config;
+    }
+
+    /// Address latched onto, if any
+    pub fn latched(&self) -> Option<SocketAddr> {
+        self.latched.map(|source| source.addr)
+    }
+
+    /// Forget the latched address, e.g. when the SDP moves the media
+    pub fn reset(&mut self) {
+        self.latched = None;
+        self.candidate = None;
+    }
+
+    /// Account for a packet received from `from`; the address to send to
+    /// from now on when it changes
+    ///
+    /// `payload_types` are those negotiated; packets of other types are
+    /// ignored.
+    pub fn observe(
+        &mut self,
+        from: SocketAddr,
+        packet: &RtpPacket,
+        payload_types: &[u8],
+    ) -> Option<SocketAddr> {
+        if !self.config.enabled {
+            return None;
+        }
+        if let Some(latched) = self.latched {
+            if latched.addr == from {
+                // The latched source is still sending
+                self.candidate = None;
+                return None;
+            }
+        }
+        if !payload_types.contains(&packet.payload_type) {
+            return None;
+        }
+
+        let source = Source {
+            addr: from,
+            ssrc: packet.ssrc,
+        };
+        let packets = match self.candidate {
+            Some((candidate, packets))
+                if candidate.addr == source.addr && candidate.ssrc == source.ssrc =>
+            {
+                packets + 1
+            }
+            _ => 1,
+        };
+        let needed = match self.latched {
+            Some(latched) if latched.ssrc != source.ssrc => self.config.relatch_packets,
+            _ => self.config.confirm_packets,
+        };
+        if packets < needed.max(1) {
+            self.candidate = Some((source, packets));
+            return None;
+        }
+        self.latched = Some(source);
+        self.candidate = None;
+        Some(from)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bytes::Bytes;
+
+    fn packet(payload_type: u8, ssrc: u32) -> RtpPacket {
+        RtpPacket::new(payload_type, 1, 0, ssrc, Bytes::from_static(&[0xff; 160]))
+    }
+
+    #[test]
+    fn test_latching_only_for_private_sdp_behind_public_signaling() {
+        let private: IpAddr = "192.168.1.50".parse().unwrap();
+        let public: IpAddr = "203.0.113.7".parse().unwrap();
+        assert!(LatchConfig::for_leg(private, Some(public)).enabled);
+        assert!(!LatchConfig::for_leg(private, Some("10.0.0.1".parse().unwrap())).enabled);
+        assert!(!LatchConfig::for_leg(public, Some(public)).enabled);
+        assert!(!LatchConfig::for_leg(private, None).enabled);
+        assert!(LatchConfig::for_leg("fd00::5".parse().unwrap(), Some("2001:db8::1".parse().unwrap())).enabled);
+        assert!(!is_public("127.0.0.1".parse().unwrap()));
+    }
+
+    #[test]
+    fn test_latch_ignores_probes_and_follows_handover() {
+        let nat: SocketAddr = "203.0.113.7:40000".parse().unwrap();
+        let scanner: SocketAddr = "198.51.100.9:5060".parse().unwrap();
+        let handover: SocketAddr = "198.51.100.20:61000".parse().unwrap();
+        let mut latch = RtpLatch::new(LatchConfig {
+            relatch_packets: 5,
+            ..LatchConfig::enabled()
+        });
+
+        // Unnegotiated payload type, and a lone packet between the phone's
+        assert_eq!(latch.observe(scanner, &packet(96, 1), &[0, 101]), None);
+        assert_eq!(latch.observe(nat, &packet(0, 0xabc), &[0, 101]), None);
+        assert_eq!(latch.observe(nat, &packet(0, 0xabc), &[0, 101]), None);
+        assert_eq!(latch.observe(scanner, &packet(0, 1), &[0, 101]), None);
+        assert_eq!(latch.observe(nat, &packet(0, 0xabc), &[0, 101]), None);
+        assert_eq!(latch.observe(nat, &packet(0, 0xabc), &[0, 101]), None);
+        assert_eq!(latch.observe(nat, &packet(0, 0xabc), &[0, 101]), Some(nat));
+        assert_eq!(latch.latched(), Some(nat));
+
+        // Another SSRC from elsewhere must persist
+        for _ in 0..4 {
+            assert_eq!(latch.observe(scanner, &packet(0, 2), &[0, 101]), None);
+        }
+        assert_eq!(latch.observe(nat, &packet(0, 0xabc), &[0, 101]), None);
+        assert_eq!(latch.observe(scanner, &packet(0, 2), &[0, 101]), None);
+        assert_eq!(latch.latched(), Some(nat));
+
+        // The phone's own stream moving is followed quickly
+        assert_eq!(latch.observe(handover, &packet(0, 0xabc), &[0, 101]), None);
+        assert_eq!(latch.observe(handover, &packet(0, 0xabc), &[0, 101]), None);
+        assert_eq!(latch.observe(handover, &packet(0, 0xabc), &[0, 101]), Some(handover));
+
+        latch.set_config(LatchConfig::default());
+        assert_eq!(latch.observe(nat, &packet(0, 0xabc), &[0]), None);
+        assert_eq!(latch.latched(), Some(handover));
+    }
+}
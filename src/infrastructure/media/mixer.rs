@@ -1,6 +1,11 @@
 /// Audio mixer for conference calls
-use std::collections::HashMap;
-use std::sync::Arc;
+use super::codec::G711Type;
+use super::rtp::{AudioLevel, RtpPacket};
+use bytes::Bytes;
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
 use tokio::sync::RwLock;
 use tracing::{debug, info};
 use uuid::Uuid;
@@ -8,6 +13,35 @@ use uuid::Uuid;
 /// Audio sample format
 pub type AudioSample = i16;
 
+/// Mean absolute sample value from which a frame counts as speech
+pub const SPEECH_LEVEL: i32 = 500;
+
+/// Reported audio level (-dBov) from which a packet counts as speech, about
+/// [`SPEECH_LEVEL`]
+pub const SPEECH_LEVEL_DBOV: u8 = 35;
+
+/// Default reported audio level (-dBov) below which packets are not decoded
+pub const DEFAULT_SILENCE_THRESHOLD_DBOV: u8 = 60;
+
+/// Samples over which the conference fades down to the ducked gain when an
+/// announcement starts, and back up when it ends (10 ms at 8 kHz)
+pub const DUCK_RAMP_SAMPLES: usize = 80;
+
+/// Gain of the conference audio at `position` of an announcement of `len`
+/// samples: ramps from 1.0 to `duck_gain` and back at the edges
+pub fn duck_envelope(position: usize, len: usize, duck_gain: f32) -> f32 {
+    if position >= len {
+        return 1.0;
+    }
+    let ramp = DUCK_RAMP_SAMPLES.min(len / 2).max(1);
+    let edge = position.min(len - 1 - position);
+    if edge >= ramp {
+        duck_gain
+    } else {
+        1.0 - (1.0 - duck_gain) * edge as f32 / ramp as f32
+    }
+}
+
 /// Audio frame (collection of samples)
 #[derive(Debug, Clone)]
 pub struct AudioFrame {
@@ -41,6 +75,15 @@ impl AudioFrame {
     pub fn is_empty(&self) -> bool {
         self.samples.is_empty()
     }
+
+    /// Mean absolute sample value
+    pub fn level(&self) -> i32 {
+        if self.samples.is_empty() {
+            return 0;
+        }
+        let sum: i64 = self.samples.iter().map(|s| (*s as i64).abs()).sum();
+        (sum / self.samples.len() as i64) as i32
+    }
 }
 
 /// Participant audio stream
@@ -49,6 +92,11 @@ pub struct ParticipantStream {
     pub participant_id: Uuid,
     pub is_muted: bool,
     pub gain: f32, // Volume gain (0.0 to 2.0, 1.0 = normal)
+    /// Last time the participant was heard talking
+    pub last_voice: Option<Instant>,
+    /// Level of the last mixed packet: as the sender reported it (RFC 6464)
+    /// or computed from the decoded audio
+    pub audio_level: Option<AudioLevel>,
 }
 
 impl ParticipantStream {
@@ -57,6 +105,8 @@ impl ParticipantStream {
             participant_id,
             is_muted: false,
             gain: 1.0,
+            last_voice: None,
+            audio_level: None,
         }
     }
 
@@ -76,11 +126,57 @@ impl ParticipantStream {
     }
 }
 
+/// Encoded audio of one participant, decoded only when it is mixed
+#[derive(Debug, Clone)]
+pub struct EncodedAudio {
+    pub payload_type: u8,
+    pub payload: Bytes,
+    /// Level the sender put in the packet, when it negotiated RFC 6464
+    pub audio_level: Option<AudioLevel>,
+}
+
+impl EncodedAudio {
+    /// Audio of a packet whose audio level extension, if negotiated, has
+    /// local ID `audio_level_id`
+    pub fn from_packet(packet: &RtpPacket, audio_level_id: Option<u8>) -> Self {
+        Self {
+            payload_type: packet.payload_type,
+            payload: packet.payload.clone(),
+            audio_level: audio_level_id.and_then(|id| packet.audio_level(id)),
+        }
+    }
+}
+
+/// Audio played into the mix for everyone, e.g. an entry tone
+struct Announcement {
+    samples: Arc<Vec<AudioSample>>,
+    duck_gain: f32,
+    /// How far each listener got (`None` for mixes sent to no participant)
+    positions: HashMap<Option<Uuid>, usize>,
+    /// Participants yet to hear it to the end
+    pending: HashSet<Uuid>,
+}
+
+/// Decoding work of a mixer
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct MixerStats {
+    /// Packets decoded for mixing
+    pub decoded: u64,
+    /// Packets left undecoded because their reported level was silence
+    pub skipped: u64,
+}
+
 /// Audio mixer for combining multiple audio streams
 pub struct AudioMixer {
     streams: Arc<RwLock<HashMap<Uuid, ParticipantStream>>>,
     sample_rate: u32,
     channels: u8,
+    /// Reported level (-dBov) below which packets are not decoded
+    silence_threshold: u8,
+    decoded: AtomicU64,
+    skipped: AtomicU64,
+    /// Announcements, played one after the other
+    announcements: Mutex<VecDeque<Announcement>>,
 }
 
 impl AudioMixer {
@@ -90,9 +186,20 @@ impl AudioMixer {
             streams: Arc::new(RwLock::new(HashMap::new())),
             sample_rate,
             channels,
+            silence_threshold: DEFAULT_SILENCE_THRESHOLD_DBOV,
+            decoded: AtomicU64::new(0),
+            skipped: AtomicU64::new(0),
+            announcements: Mutex::new(VecDeque::new()),
         }
     }
 
+    /// Reported level (-dBov) below which packets without voice are not
+    /// decoded
+    pub fn with_silence_threshold(mut self, dbov: u8) -> Self {
+        self.silence_threshold = dbov.min(127);
+        self
+    }
+
     /// Add participant stream
     pub async fn add_stream(&self, participant_id: Uuid) {
         let stream = ParticipantStream::new(participant_id);
@@ -105,6 +212,11 @@ impl AudioMixer {
     pub async fn remove_stream(&self, participant_id: Uuid) {
         let mut streams = self.streams.write().await;
         streams.remove(&participant_id);
+        let mut announcements = self.announcements.lock().unwrap();
+        for announcement in announcements.iter_mut() {
+            announcement.pending.remove(&participant_id);
+        }
+        announcements.retain(|a| !a.pending.is_empty());
         info!("Removed audio stream for participant {}", participant_id);
     }
 
@@ -135,6 +247,74 @@ impl AudioMixer {
         Ok(())
     }
 
+    /// Restart a participant's silence timer, e.g. when given the floor
+    pub async fn reset_voice_activity(&self, participant_id: Uuid) {
+        if let Some(stream) = self.streams.write().await.get_mut(&participant_id) {
+            stream.last_voice = Some(Instant::now());
+        }
+    }
+
+    /// Last time an unmuted participant was heard talking
+    pub async fn last_voice(&self, participant_id: Uuid) -> Option<Instant> {
+        self.streams
+            .read()
+            .await
+            .get(&participant_id)
+            .and_then(|s| s.last_voice)
+    }
+
+    /// Play `samples` to every participant, the conference ducked to
+    /// `duck_gain` meanwhile; waits for announcements already playing
+    pub async fn announce(&self, samples: Arc<Vec<AudioSample>>, duck_gain: f32) {
+        let pending: HashSet<Uuid> = self.streams.read().await.keys().copied().collect();
+        if samples.is_empty() || pending.is_empty() {
+            return;
+        }
+        self.announcements.lock().unwrap().push_back(Announcement {
+            samples,
+            duck_gain: duck_gain.clamp(0.0, 1.0),
+            positions: HashMap::new(),
+            pending,
+        });
+    }
+
+    /// An announcement is playing or waiting to
+    pub fn is_announcing(&self) -> bool {
+        !self.announcements.lock().unwrap().is_empty()
+    }
+
+    /// Duck `mixed` and add the next `mixed.len()` samples of the current
+    /// announcement, as heard by `listener`
+    fn overlay_announcement(&self, mixed: &mut Vec<i32>, listener: Option<Uuid>) {
+        let mut announcements = self.announcements.lock().unwrap();
+        let Some(announcement) = announcements.front_mut() else {
+            return;
+        };
+        let len = announcement.samples.len();
+        let position = announcement.positions.entry(listener).or_insert(0);
+        if mixed.is_empty() {
+            // Nobody talks: the announcement alone, one 20 ms frame
+            mixed.resize((self.sample_rate as usize / 50) * self.channels as usize, 0);
+        }
+
+        for (i, sample) in mixed.iter_mut().enumerate() {
+            let at = *position + i;
+            let gain = duck_envelope(at, len, announcement.duck_gain);
+            let overlay = announcement.samples.get(at).copied().unwrap_or(0) as i32;
+            *sample = (*sample as f32 * gain) as i32 + overlay;
+        }
+        *position = (*position + mixed.len()).min(len);
+
+        if *position == len {
+            if let Some(listener) = listener {
+                announcement.pending.remove(&listener);
+            }
+        }
+        if announcement.pending.is_empty() {
+            announcements.pop_front();
+        }
+    }
+
     /// Mix audio frames from multiple participants
     /// Excludes the specified participant from the mix (for their own audio)
     pub async fn mix_frames(
@@ -142,12 +322,99 @@ impl AudioMixer {
         frames: Vec<(Uuid, AudioFrame)>,
         exclude_participant: Option<Uuid>,
     ) -> AudioFrame {
+        let frames = frames
+            .into_iter()
+            .map(|(participant_id, frame)| (participant_id, frame, None))
+            .collect();
+        self.mix(frames, exclude_participant).await
+    }
+
+    /// Mix the encoded audio of multiple participants, excluding one
+    ///
+    /// Packets whose reported level is silence are not decoded: they would
+    /// add nothing to the mix. Packets without a reported level are decoded
+    /// and measured. Only G.711 is decoded here; other payloads are left
+    /// out of the mix.
+    pub async fn mix_packets(
+        &self,
+        packets: Vec<(Uuid, EncodedAudio)>,
+        exclude_participant: Option<Uuid>,
+    ) -> AudioFrame {
+        let mut frames = Vec::with_capacity(packets.len());
+        let mut silent = Vec::new();
+        for (participant_id, audio) in packets {
+            if let Some(level) = audio.audio_level {
+                if !level.voice && !level.is_louder_than(self.silence_threshold) {
+                    self.skipped.fetch_add(1, Ordering::Relaxed);
+                    silent.push((participant_id, level));
+                    continue;
+                }
+            }
+            let codec = match G711Type::from_payload_type(audio.payload_type) {
+                Some(codec) => codec,
+                None => {
+                    debug!("Not mixing payload type {} of {}", audio.payload_type, participant_id);
+                    continue;
+                }
+            };
+            self.decoded.fetch_add(1, Ordering::Relaxed);
+            let samples = codec.decode(&audio.payload);
+            frames.push((
+                participant_id,
+                AudioFrame::new(samples, self.sample_rate, self.channels, 0),
+                audio.audio_level,
+            ));
+        }
+
+        if !silent.is_empty() {
+            let mut streams = self.streams.write().await;
+            for (participant_id, level) in silent {
+                if let Some(stream) = streams.get_mut(&participant_id) {
+                    stream.audio_level = Some(level);
+                }
+            }
+        }
+        self.mix(frames, exclude_participant).await
+    }
+
+    /// Decoding done and avoided so far
+    pub fn stats(&self) -> MixerStats {
+        MixerStats {
+            decoded: self.decoded.load(Ordering::Relaxed),
+            skipped: self.skipped.load(Ordering::Relaxed),
+        }
+    }
+
+    /// Up to `count` unmuted participants heard above the silence
+    /// threshold in their last packet, loudest first
+    pub async fn loudest_speakers(&self, count: usize) -> Vec<Uuid> {
         let streams = self.streams.read().await;
+        let mut speakers: Vec<(u8, Uuid)> = streams
+            .values()
+            .filter(|s| !s.is_muted)
+            .filter_map(|s| {
+                let level = s.audio_level?;
+                level
+                    .is_louder_than(self.silence_threshold)
+                    .then_some((level.level, s.participant_id))
+            })
+            .collect();
+        speakers.sort();
+        speakers.into_iter().take(count).map(|(_, id)| id).collect()
+    }
+
+    /// Mix frames, each with the level its sender reported if any
+    async fn mix(
+        &self,
+        frames: Vec<(Uuid, AudioFrame, Option<AudioLevel>)>,
+        exclude_participant: Option<Uuid>,
+    ) -> AudioFrame {
+        let mut streams = self.streams.write().await;
 
         // Find maximum frame length
-        let max_len = frames.iter().map(|(_, f)| f.len()).max().unwrap_or(0);
+        let max_len = frames.iter().map(|(_, f, _)| f.len()).max().unwrap_or(0);
 
-        if max_len == 0 {
+        if max_len == 0 && !self.is_announcing() {
             return AudioFrame::new(Vec::new(), self.sample_rate, self.channels, 0);
         }
 
@@ -155,18 +422,29 @@ impl AudioMixer {
         let mut mixed_samples = vec![0i32; max_len];
 
         // Mix all frames
-        for (participant_id, frame) in frames.iter() {
-            // Skip excluded participant
-            if Some(*participant_id) == exclude_participant {
-                continue;
-            }
-
+        for (participant_id, frame, reported) in frames.iter() {
             // Get stream info
-            let stream = match streams.get(participant_id) {
+            let stream = match streams.get_mut(participant_id) {
                 Some(s) => s,
                 None => continue,
             };
 
+            // Track who is talking, for silence detection and speaker ranking
+            let talking = match reported {
+                Some(level) => level.voice || level.is_louder_than(SPEECH_LEVEL_DBOV),
+                None => frame.level() >= SPEECH_LEVEL,
+            };
+            stream.audio_level =
+                Some(reported.unwrap_or_else(|| AudioLevel::from_samples(&frame.samples)));
+            if !stream.is_muted && talking {
+                stream.last_voice = Some(Instant::now());
+            }
+
+            // Skip excluded participant
+            if Some(*participant_id) == exclude_participant {
+                continue;
+            }
+
             // Skip if muted
             if stream.is_muted {
                 continue;
@@ -181,6 +459,8 @@ impl AudioMixer {
             }
         }
 
+        self.overlay_announcement(&mut mixed_samples, exclude_participant);
+
         // Clamp and convert to i16
         let output_samples: Vec<AudioSample> = mixed_samples
             .iter()
@@ -393,6 +673,181 @@ mod tests {
         assert_eq!(mixed.samples, vec![50, 100, 150]);
     }
 
+    #[tokio::test]
+    async fn test_voice_activity() {
+        let mixer = AudioMixer::new(8000, 1);
+
+        let talker = Uuid::new_v4();
+        let quiet = Uuid::new_v4();
+        let muted = Uuid::new_v4();
+        for p in [talker, quiet, muted] {
+            mixer.add_stream(p).await;
+        }
+        mixer.mute_participant(muted).await.unwrap();
+
+        let speech = AudioFrame::new(vec![2000, -2000, 1500, -1500], 8000, 1, 0);
+        let noise = AudioFrame::new(vec![10, -10, 5, -5], 8000, 1, 0);
+        let frames = vec![(talker, speech.clone()), (quiet, noise), (muted, speech)];
+
+        // Talking counts even in the mix sent back to the talker
+        mixer.mix_frames(frames, Some(talker)).await;
+
+        assert!(mixer.last_voice(talker).await.is_some());
+        assert!(mixer.last_voice(quiet).await.is_none());
+        assert!(mixer.last_voice(muted).await.is_none());
+    }
+
+    fn pcmu(samples: &[i16], audio_level: Option<AudioLevel>) -> EncodedAudio {
+        EncodedAudio {
+            payload_type: 0,
+            payload: G711Type::PCMU.encode(samples),
+            audio_level,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_reported_levels_rank_speakers_and_skip_silence() {
+        let mixer = AudioMixer::new(8000, 1);
+        let (loud, soft, silent, measured) =
+            (Uuid::new_v4(), Uuid::new_v4(), Uuid::new_v4(), Uuid::new_v4());
+        for p in [loud, soft, silent, measured] {
+            mixer.add_stream(p).await;
+        }
+
+        let speech = vec![3000i16; 160];
+        let packets = vec![
+            (loud, pcmu(&speech, Some(AudioLevel::new(true, 12)))),
+            (soft, pcmu(&speech, Some(AudioLevel::new(true, 40)))),
+            (silent, pcmu(&speech, Some(AudioLevel::new(false, 127)))),
+            // No extension: decoded and measured (3000 is about -21 dBov)
+            (measured, pcmu(&speech, None)),
+        ];
+        let mixed = mixer.mix_packets(packets, None).await;
+        assert_eq!(mixed.len(), 160);
+
+        assert_eq!(mixer.stats(), MixerStats { decoded: 3, skipped: 1 });
+        assert_eq!(mixer.loudest_speakers(2).await, vec![loud, measured]);
+        assert_eq!(mixer.loudest_speakers(5).await, vec![loud, measured, soft]);
+        assert!(mixer.last_voice(soft).await.is_some());
+        assert!(mixer.last_voice(silent).await.is_none());
+        assert!(mixer.last_voice(measured).await.is_some());
+
+        mixer.mute_participant(loud).await.unwrap();
+        assert_eq!(mixer.loudest_speakers(1).await, vec![measured]);
+    }
+
+    /// Conference load: 20 participants, 2 talking, 1s of 20ms packets
+    #[tokio::test]
+    async fn test_conference_load_decoding_saved_by_audio_levels() {
+        const PARTICIPANTS: usize = 20;
+        const TALKERS: usize = 2;
+        const ROUNDS: usize = 50;
+
+        let speech = vec![3000i16; 160];
+        let silence = vec![0i16; 160];
+        let run = |with_levels: bool| {
+            let (speech, silence) = (speech.clone(), silence.clone());
+            async move {
+                let mixer = AudioMixer::new(8000, 1);
+                let participants: Vec<Uuid> = (0..PARTICIPANTS).map(|_| Uuid::new_v4()).collect();
+                for p in &participants {
+                    mixer.add_stream(*p).await;
+                }
+                for _ in 0..ROUNDS {
+                    // Each participant hears everyone but themselves
+                    for listener in &participants {
+                        let packets = participants
+                            .iter()
+                            .enumerate()
+                            .map(|(i, p)| {
+                                let (samples, level) = if i < TALKERS {
+                                    (&speech, AudioLevel::new(true, 21))
+                                } else {
+                                    (&silence, AudioLevel::new(false, 127))
+                                };
+                                (*p, pcmu(samples, with_levels.then_some(level)))
+                            })
+                            .collect();
+                        mixer.mix_packets(packets, Some(*listener)).await;
+                    }
+                }
+                mixer.stats()
+            }
+        };
+
+        let measured = run(false).await;
+        let reported = run(true).await;
+        let mixes = (ROUNDS * PARTICIPANTS) as u64;
+        assert_eq!(measured, MixerStats { decoded: mixes * PARTICIPANTS as u64, skipped: 0 });
+        assert_eq!(
+            reported,
+            MixerStats {
+                decoded: mixes * TALKERS as u64,
+                skipped: mixes * (PARTICIPANTS - TALKERS) as u64,
+            }
+        );
+    }
+
+    #[tokio::test]
+    async fn test_announcement_ducks_conference() {
+        let mixer = AudioMixer::new(8000, 1);
+        let (talker, listener) = (Uuid::new_v4(), Uuid::new_v4());
+        mixer.add_stream(talker).await;
+        mixer.add_stream(listener).await;
+
+        // 40 ms of silence announced: only the ducking is heard
+        mixer.announce(Arc::new(vec![0; 320]), 0.5).await;
+        assert!(mixer.is_announcing());
+
+        let speech = || vec![(talker, AudioFrame::new(vec![1000; 160], 8000, 1, 0))];
+        let first = mixer.mix_frames(speech(), Some(listener)).await;
+        let second = mixer.mix_frames(speech(), Some(listener)).await;
+        let samples: Vec<i16> = first.samples.into_iter().chain(second.samples).collect();
+
+        // Fades down over the ramp, holds, and fades back up at the end
+        assert_eq!(samples[0], 1000);
+        assert_eq!(samples[DUCK_RAMP_SAMPLES / 2], 750);
+        assert!(samples[..DUCK_RAMP_SAMPLES].windows(2).all(|w| w[0] >= w[1]));
+        assert!(samples[DUCK_RAMP_SAMPLES..320 - DUCK_RAMP_SAMPLES].iter().all(|&s| s == 500));
+        assert!(samples[320 - DUCK_RAMP_SAMPLES..].windows(2).all(|w| w[0] <= w[1]));
+        assert_eq!(samples[319], 1000);
+
+        // The talker has not heard it yet, so it keeps playing for them
+        assert!(mixer.is_announcing());
+        let heard = mixer
+            .mix_frames(vec![(listener, AudioFrame::new(vec![0; 160], 8000, 1, 0))], Some(talker))
+            .await;
+        assert!(heard.samples.iter().all(|&s| s == 0));
+        mixer.remove_stream(talker).await;
+        assert!(!mixer.is_announcing());
+
+        // Ducking is over for a participant who joins afterwards
+        let newcomer = Uuid::new_v4();
+        mixer.add_stream(newcomer).await;
+        let after = mixer
+            .mix_frames(
+                vec![(newcomer, AudioFrame::new(vec![1000; 160], 8000, 1, 0))],
+                Some(listener),
+            )
+            .await;
+        assert!(after.samples.iter().all(|&s| s == 1000));
+    }
+
+    #[tokio::test]
+    async fn test_announcement_heard_without_talkers() {
+        let mixer = AudioMixer::new(8000, 1);
+        let listener = Uuid::new_v4();
+        mixer.add_stream(listener).await;
+
+        mixer.announce(Arc::new(vec![300; 200]), 0.5).await;
+        let first = mixer.mix_frames(Vec::new(), Some(listener)).await;
+        assert_eq!(first.samples, vec![300; 160]);
+        let second = mixer.mix_frames(Vec::new(), Some(listener)).await;
+        assert_eq!(&second.samples[..40], &[300; 40][..]);
+        assert!(second.samples[40..].iter().all(|&s| s == 0));
+        assert!(!mixer.is_announcing());
+    }
+
     #[test]
     fn test_agc_process() {
         let mut agc = AutomaticGainControl::new(1000.0);
@@ -1,24 +1,54 @@
 //! Media processing implementations
 
+pub mod bandwidth;
 pub mod bridge;
+pub mod capacity;
 pub mod codec;
+pub mod decode_health;
+pub mod latch;
 pub mod mixer;
 pub mod moh;
+pub mod port_allocator;
+pub mod ptime;
+pub mod relay;
+pub mod ringback;
 pub mod rtp;
 pub mod srtp;
 pub mod stream;
+pub mod tap;
 
-pub use bridge::{MediaBridge, MediaBridgeManager};
-pub use codec::{CodecInfo, CodecNegotiator, G711Type, PcmaCodec, PcmuCodec};
-pub use mixer::{AudioFrame, AudioMixer, AutomaticGainControl, ParticipantStream};
-pub use moh::{MohConfig, MohPlayer, MohState, ToneGenerator};
+pub use bandwidth::{
+    BandwidthConfig, BandwidthEstimate, BandwidthEstimator, BandwidthMonitor, QualityWarning,
+};
+pub use bridge::{BridgeDirection, BridgeLeg, MediaBridge, MediaBridgeManager};
+pub use capacity::{
+    CapacityConfig, CapacityEvent, CapacityMonitor, CapacitySnapshot, CodecProfile, TrunkUsage,
+};
+pub use codec::{CodecInfo, CodecNegotiator, G711Type, PayloadMap, PcmaCodec, PcmuCodec};
+pub use decode_health::{DecodeCounters, DecodeHealth};
+pub use latch::{LatchConfig, RtpLatch};
+pub use mixer::{
+    AudioFrame, AudioMixer, AutomaticGainControl, EncodedAudio, MixerStats, ParticipantStream,
+};
+pub use moh::{
+    MohClass, MohClassConfig, MohClassRegistry, MohClassesConfig, MohConfig, MohNowPlaying, MohPlayer, MohResume,
+    MohState, MohTrack, PlaylistStream, ToneGenerator, DEFAULT_MOH_CLASS,
+};
+pub use port_allocator::RtpPortAllocator;
+pub use ptime::{PacketFormat, PtimeAdapter, DEFAULT_PTIME_MS};
+pub use relay::MediaRelay;
+pub use ringback::{
+    Ringback, RingbackConfig, RingbackPlayer, RingbackPolicy, RingbackSettings, RingbackSource,
+    RingbackTone, TonePlan,
+};
 pub use rtp::{
-    Goodbye, JitterBuffer, JitterBufferConfig, JitterBufferStats, ReceiverReport, RtcpError,
-    RtcpPacket, RtpError, RtpPacket, RtpSession, RtpStats, SenderReport, SourceDescription,
+    Goodbye, JitterBuffer, JitterBufferConfig, JitterBufferStats, MuxedPacket, ReceiverReport,
+    Remb, RtcpError, RtcpPacket, RtpError, RtpPacket, RtpSession, RtpStats, SenderReport, SourceDescription,
     SsrcGenerator,
 };
 pub use srtp::{
     MediaCryptoContext, SrtpContext, SrtcpContext, SrtpError, SrtpMasterKey,
     SrtpProfile, SrtpSessionKeys, derive_session_keys,
 };
-pub use stream::{MediaStream, StreamDirection};
+pub use stream::{MediaStream, MediaStreamGuard, StreamDirection};
+pub use tap::{media_tap, MediaTap, PcmReceiver, DEFAULT_TAP_CHUNKS};
@@ -1,10 +1,28 @@
 //! Music on Hold (MOH) implementation
 //!
-//! Provides audio playback for callers on hold
-
+//! Provides audio playback for callers on hold. Besides a single source,
+//! a player can play a named MOH class: a playlist of audio library files,
+//! in order or shuffled, with consecutive tracks crossfaded so there is no
+//! gap or click between them. A class is selected per call from the queue
+//! the call waits in, the tenant's branding or the `default` class.
+
+use crate::domain::audio::{AudioCategory, AudioLibrary, AudioReference, NARROWBAND_RATE};
+use rand::rngs::StdRng;
+use rand::seq::SliceRandom;
+use rand::SeedableRng;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::Arc;
+use std::time::Instant;
 use tokio::sync::RwLock;
-use tracing::{debug, info};
+use tracing::{debug, info, warn};
+
+/// Class played to calls that select no other
+pub const DEFAULT_MOH_CLASS: &str = "default";
+
+/// Reference kind recorded on audio files played by a MOH class
+const MOH_CLASS_REFERENCE: &str = "moh";
 
 /// Music on Hold state
 #[derive(Debug, Clone, PartialEq, Eq)]
@@ -40,15 +58,16 @@ impl Default for MohConfig {
 pub struct MohPlayer {
     config: MohConfig,
     state: Arc<RwLock<MohState>>,
+    /// Set once by `close()`
+    closed: AtomicBool,
+    /// Playlist of the MOH class this player plays, if any
+    playlist: Option<std::sync::Mutex<PlaylistClock>>,
 }
 
 impl MohPlayer {
     /// Create new MOH player with default configuration
     pub fn new() -> Self {
-        Self {
-            config: MohConfig::default(),
-            state: Arc::new(RwLock::new(MohState::Idle)),
-        }
+        Self::with_config(MohConfig::default())
     }
 
     /// Create MOH player with custom configuration
@@ -56,13 +75,54 @@ impl MohPlayer {
         Self {
             config,
             state: Arc::new(RwLock::new(MohState::Idle)),
+            closed: AtomicBool::new(false),
+            playlist: None,
         }
     }
 
+    /// Player of a MOH class; `seed` draws its shuffled orders
+    pub fn with_class(class: Arc<MohClass>, seed: u64) -> Self {
+        let mut player = Self::with_config(MohConfig {
+            source: format!("class:{}", class.name()),
+            ..Default::default()
+        });
+        player.playlist = Some(std::sync::Mutex::new(PlaylistClock {
+            stream: PlaylistStream::new(class, seed),
+            playing_since: None,
+            played: 0,
+        }));
+        player
+    }
+
+    /// Next `count` samples of the class playlist, for the media sender
+    pub fn read_frame(&self, count: usize) -> Option<Vec<i16>> {
+        let mut clock = self.playlist.as_ref()?.lock().unwrap();
+        let mut frame = vec![0; count];
+        clock.stream.read(&mut frame);
+        clock.played += count as u64;
+        Some(frame)
+    }
+
+    /// Track of the class playlist playing now
+    pub fn now_playing(&self) -> Option<MohNowPlaying> {
+        let mut clock = self.playlist.as_ref()?.lock().unwrap();
+        clock.catch_up();
+        Some(clock.stream.now_playing())
+    }
+
+    /// Audio source this player streams
+    pub fn source(&self) -> &str {
+        &self.config.source
+    }
+
     /// Start playing music on hold
     pub async fn start(&self) -> Result<(), String> {
         let mut state = self.state.write().await;
 
+        if self.closed.load(Ordering::SeqCst) {
+            return Err("MOH player is closed".to_string());
+        }
+
         if *state == MohState::Playing {
             return Err("MOH is already playing".to_string());
         }
@@ -75,6 +135,11 @@ impl MohPlayer {
         // 4. Sending to the media stream
 
         info!("Starting MOH playback from: {}", self.config.source);
+        if let Some(playlist) = &self.playlist {
+            let mut clock = playlist.lock().unwrap();
+            clock.playing_since = Some(Instant::now());
+            clock.played = 0;
+        }
         *state = MohState::Playing;
 
         Ok(())
@@ -92,6 +157,12 @@ impl MohPlayer {
         // This would stop the playback task and cleanup resources
 
         info!("Stopping MOH playback");
+        if let Some(playlist) = &self.playlist {
+            let mut clock = playlist.lock().unwrap();
+            clock.catch_up();
+            clock.playing_since = None;
+            clock.stream.save_resume_point();
+        }
         *state = MohState::Idle;
 
         Ok(())
@@ -106,6 +177,36 @@ impl MohPlayer {
     pub async fn state(&self) -> MohState {
         self.state.read().await.clone()
     }
+
+    /// Stop playback for good
+    ///
+    /// Safe to call more than once; a closed player cannot be restarted.
+    pub async fn close(&self) {
+        if self.closed.swap(true, Ordering::SeqCst) {
+            return;
+        }
+        let _ = self.stop().await;
+        debug!("MOH player closed");
+    }
+
+    /// Whether `close()` was called
+    pub fn is_closed(&self) -> bool {
+        self.closed.load(Ordering::SeqCst)
+    }
+}
+
+impl Drop for MohPlayer {
+    fn drop(&mut self) {
+        if self.is_closed() {
+            return;
+        }
+        if let Ok(mut state) = self.state.try_write() {
+            if *state == MohState::Playing {
+                warn!("MOH player dropped without close() while playing");
+                *state = MohState::Idle;
+            }
+        }
+    }
 }
 
 impl Default for MohPlayer {
@@ -114,6 +215,440 @@ impl Default for MohPlayer {
     }
 }
 
+/// Where a class's playlist starts for a newly held call
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum MohResume {
+    /// From the start of the playlist, for every call
+    #[default]
+    Restart,
+    /// Where the class's previous held call left off
+    Continue,
+}
+
+/// One MOH class: a playlist of MOH files from the audio library
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct MohClassConfig {
+    /// Tenant owning the files; global files without one
+    pub tenant: Option<String>,
+    /// Audio library ids of the files, in playing order
+    pub files: Vec<String>,
+    /// Play the files in random order, drawn again for every pass
+    pub shuffle: bool,
+    /// Gain applied to every track, in dB
+    pub gain_db: f32,
+    pub resume: MohResume,
+}
+
+impl MohClassConfig {
+    pub fn validate(&self) -> Result<(), String> {
+        if self.files.is_empty() {
+            return Err("A MOH class needs at least one file".to_string());
+        }
+        if !(-30.0..=12.0).contains(&self.gain_db) {
+            return Err("gain_db must be between -30 and 12".to_string());
+        }
+        Ok(())
+    }
+}
+
+/// Named MOH classes
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct MohClassesConfig {
+    /// Milliseconds consecutive tracks overlap while one fades into the next
+    pub crossfade_ms: u32,
+    /// Classes by name; `default` plays to calls that select no other
+    pub classes: HashMap<String, MohClassConfig>,
+}
+
+impl Default for MohClassesConfig {
+    fn default() -> Self {
+        Self {
+            crossfade_ms: 30,
+            classes: HashMap::new(),
+        }
+    }
+}
+
+impl MohClassesConfig {
+    pub fn validate(&self) -> Result<(), String> {
+        if self.crossfade_ms > 2000 {
+            return Err("crossfade_ms must be at most 2000".to_string());
+        }
+        for (name, class) in &self.classes {
+            class.validate().map_err(|e| format!("Class {}: {}", name, e))?;
+        }
+        Ok(())
+    }
+}
+
+/// A decoded file of a MOH class
+#[derive(Clone)]
+pub struct MohTrack {
+    /// Audio library id
+    pub file_id: String,
+    pub name: String,
+    /// 8 kHz mono PCM
+    pub samples: Arc<Vec<i16>>,
+}
+
+impl std::fmt::Debug for MohTrack {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("MohTrack")
+            .field("file_id", &self.file_id)
+            .field("name", &self.name)
+            .field("samples", &self.samples.len())
+            .finish()
+    }
+}
+
+/// Position in a class's playlist
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct PlaylistPosition {
+    order: Vec<usize>,
+    index: usize,
+    offset: usize,
+}
+
+/// A loaded MOH class
+///
+/// A reload builds a new class; calls already on hold keep playing the
+/// class they started with.
+#[derive(Debug)]
+pub struct MohClass {
+    name: String,
+    tracks: Vec<MohTrack>,
+    shuffle: bool,
+    /// Linear gain
+    gain: f32,
+    resume: MohResume,
+    /// Overlap of consecutive tracks, in samples
+    crossfade: usize,
+    /// Where the last held call stopped, for `MohResume::Continue`
+    resume_point: std::sync::Mutex<Option<PlaylistPosition>>,
+}
+
+impl MohClass {
+    /// `tracks` must not be empty, nor any of them
+    pub fn new(name: &str, tracks: Vec<MohTrack>, config: &MohClassConfig, crossfade_ms: u32) -> Self {
+        Self {
+            name: name.to_string(),
+            tracks,
+            shuffle: config.shuffle,
+            gain: 10f32.powf(config.gain_db / 20.0),
+            resume: config.resume,
+            crossfade: (NARROWBAND_RATE as u64 * crossfade_ms as u64 / 1000) as usize,
+            resume_point: std::sync::Mutex::new(None),
+        }
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn tracks(&self) -> &[MohTrack] {
+        &self.tracks
+    }
+}
+
+/// Track a held call hears
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct MohNowPlaying {
+    pub class: String,
+    /// Audio library id of the track
+    pub file_id: String,
+    pub name: String,
+    /// Milliseconds into the track
+    pub position_ms: u64,
+}
+
+/// Samples of a class's playlist, one stream per held call
+///
+/// The last `crossfade` samples of a track are mixed with the first ones
+/// of the next, fading linearly from one to the other; the next track then
+/// continues after the samples already mixed in.
+pub struct PlaylistStream {
+    class: Arc<MohClass>,
+    rng: StdRng,
+    position: PlaylistPosition,
+    /// Order of the next pass, drawn early to crossfade into its first track
+    next_order: Option<Vec<usize>>,
+}
+
+impl PlaylistStream {
+    pub fn new(class: Arc<MohClass>, seed: u64) -> Self {
+        let mut rng = StdRng::seed_from_u64(seed);
+        let resume_point = match class.resume {
+            MohResume::Continue => class.resume_point.lock().unwrap().clone(),
+            MohResume::Restart => None,
+        };
+        let position = resume_point.unwrap_or_else(|| PlaylistPosition {
+            order: Self::draw_order(&class, &mut rng),
+            index: 0,
+            offset: 0,
+        });
+        Self {
+            class,
+            rng,
+            position,
+            next_order: None,
+        }
+    }
+
+    fn draw_order(class: &MohClass, rng: &mut StdRng) -> Vec<usize> {
+        let mut order: Vec<usize> = (0..class.tracks.len()).collect();
+        if class.shuffle {
+            order.shuffle(rng);
+        }
+        order
+    }
+
+    pub fn class(&self) -> &Arc<MohClass> {
+        &self.class
+    }
+
+    /// Track playing now
+    pub fn current_track(&self) -> &MohTrack {
+        &self.class.tracks[self.position.order[self.position.index]]
+    }
+
+    fn upcoming_index(&mut self) -> usize {
+        let next = self.position.index + 1;
+        if next < self.position.order.len() {
+            return self.position.order[next];
+        }
+        let class = self.class.clone();
+        let rng = &mut self.rng;
+        self.next_order
+            .get_or_insert_with(|| Self::draw_order(&class, rng))[0]
+    }
+
+    /// Samples the current track overlaps the next one
+    fn fade_len(&self, current: usize, next: usize) -> usize {
+        self.class.crossfade.min(current / 2).min(next / 2)
+    }
+
+    fn advance(&mut self, start_offset: usize) {
+        self.position.index += 1;
+        if self.position.index == self.position.order.len() {
+            let class = self.class.clone();
+            self.position.order = self
+                .next_order
+                .take()
+                .unwrap_or_else(|| Self::draw_order(&class, &mut self.rng));
+            self.position.index = 0;
+        }
+        self.position.offset = start_offset;
+    }
+
+    /// Fill `out` with the next samples
+    pub fn read(&mut self, out: &mut [i16]) {
+        let class = self.class.clone();
+        for sample in out.iter_mut() {
+            let current = &class.tracks[self.position.order[self.position.index]].samples;
+            let next = &class.tracks[self.upcoming_index()].samples;
+            let fade = self.fade_len(current.len(), next.len());
+            let fade_start = current.len() - fade;
+            let offset = self.position.offset;
+            let value = if offset < fade_start {
+                current[offset] as f32
+            } else {
+                let mixed = offset - fade_start;
+                let t = (mixed as f32 + 0.5) / fade as f32;
+                current[offset] as f32 * (1.0 - t) + next[mixed] as f32 * t
+            };
+            *sample = (value * class.gain).round().clamp(i16::MIN as f32, i16::MAX as f32) as i16;
+            self.position.offset += 1;
+            if self.position.offset == current.len() {
+                self.advance(fade);
+            }
+        }
+    }
+
+    /// Move `samples` ahead without producing them
+    pub fn skip(&mut self, mut samples: u64) {
+        let class = self.class.clone();
+        while samples > 0 {
+            let current = class.tracks[self.position.order[self.position.index]].samples.len();
+            let next = class.tracks[self.upcoming_index()].samples.len();
+            let left = (current - self.position.offset) as u64;
+            if samples < left {
+                self.position.offset += samples as usize;
+                return;
+            }
+            samples -= left;
+            let fade = self.fade_len(current, next);
+            self.advance(fade);
+        }
+    }
+
+    /// Track playing now and how far into it
+    pub fn now_playing(&self) -> MohNowPlaying {
+        let track = self.current_track();
+        MohNowPlaying {
+            class: self.class.name.clone(),
+            file_id: track.file_id.clone(),
+            name: track.name.clone(),
+            position_ms: self.position.offset as u64 * 1000 / NARROWBAND_RATE as u64,
+        }
+    }
+
+    /// Let the class's next held call continue from here
+    fn save_resume_point(&self) {
+        if self.class.resume == MohResume::Continue {
+            *self.class.resume_point.lock().unwrap() = Some(self.position.clone());
+        }
+    }
+}
+
+/// Playlist of a player, kept in step with the wall clock while it plays
+///
+/// Samples not read by a media sender are skipped, so the reported track
+/// is the one a caller would hear.
+struct PlaylistClock {
+    stream: PlaylistStream,
+    playing_since: Option<Instant>,
+    /// Samples produced or skipped since playing started
+    played: u64,
+}
+
+impl PlaylistClock {
+    fn catch_up(&mut self) {
+        if let Some(since) = self.playing_since {
+            let due = since.elapsed().as_millis() as u64 * NARROWBAND_RATE as u64 / 1000;
+            if due > self.played {
+                self.stream.skip(due - self.played);
+                self.played = due;
+            }
+        }
+    }
+}
+
+/// Loaded MOH classes, by name
+///
+/// The files of a class are marked as referenced in the audio library, so
+/// they cannot be deleted while the class plays them.
+pub struct MohClassRegistry {
+    library: Arc<AudioLibrary>,
+    crossfade_ms: u32,
+    classes: std::sync::RwLock<HashMap<String, (MohClassConfig, Arc<MohClass>)>>,
+}
+
+impl MohClassRegistry {
+    pub fn new(library: Arc<AudioLibrary>) -> Self {
+        Self {
+            library,
+            crossfade_ms: MohClassesConfig::default().crossfade_ms,
+            classes: std::sync::RwLock::new(HashMap::new()),
+        }
+    }
+
+    /// Load the configured classes; classes that fail to load are skipped
+    /// and reported
+    pub fn from_config(config: &MohClassesConfig, library: Arc<AudioLibrary>) -> (Self, Vec<String>) {
+        let registry = Self {
+            crossfade_ms: config.crossfade_ms,
+            ..Self::new(library)
+        };
+        let errors = config
+            .classes
+            .iter()
+            .filter_map(|(name, class)| registry.load(name, class.clone()).err())
+            .collect();
+        (registry, errors)
+    }
+
+    /// Load a class, or reload it with a new playlist
+    ///
+    /// Calls on hold keep the playlist they started with; the next call
+    /// put on hold gets the new one.
+    pub fn load(&self, name: &str, config: MohClassConfig) -> Result<Arc<MohClass>, String> {
+        config.validate().map_err(|e| format!("MOH class {}: {}", name, e))?;
+        let tenant = config.tenant.as_deref();
+        let mut tracks = Vec::with_capacity(config.files.len());
+        for id in &config.files {
+            let file = self
+                .library
+                .get(tenant, id)
+                .filter(|file| file.category == AudioCategory::Moh)
+                .ok_or_else(|| format!("MOH class {}: MOH file {} not found", name, id))?;
+            let samples = self
+                .library
+                .pcm(tenant, id)
+                .map_err(|e| format!("MOH class {}: {}", name, e))?;
+            if samples.is_empty() {
+                return Err(format!("MOH class {}: file {} is empty", name, id));
+            }
+            tracks.push(MohTrack {
+                file_id: id.clone(),
+                name: file.name,
+                samples,
+            });
+        }
+        let class = Arc::new(MohClass::new(name, tracks, &config, self.crossfade_ms));
+
+        let reference = AudioReference::new(MOH_CLASS_REFERENCE, name);
+        for id in &config.files {
+            self.library.add_reference(tenant, id, reference.clone());
+        }
+        let previous = self
+            .classes
+            .write()
+            .unwrap()
+            .insert(name.to_string(), (config.clone(), class.clone()));
+        if let Some((previous, _)) = previous {
+            self.release_files(&previous, Some(&config), &reference);
+        }
+        info!("MOH class {} loaded with {} tracks", name, class.tracks.len());
+        Ok(class)
+    }
+
+    /// Drop a class; calls on hold keep playing it
+    pub fn remove(&self, name: &str) -> bool {
+        let removed = self.classes.write().unwrap().remove(name);
+        if let Some((config, _)) = &removed {
+            self.release_files(config, None, &AudioReference::new(MOH_CLASS_REFERENCE, name));
+        }
+        removed.is_some()
+    }
+
+    /// Release the files of `previous` that `current` no longer plays
+    fn release_files(&self, previous: &MohClassConfig, current: Option<&MohClassConfig>, reference: &AudioReference) {
+        for id in &previous.files {
+            let kept = current.is_some_and(|current| current.tenant == previous.tenant && current.files.contains(id));
+            if !kept {
+                self.library.remove_reference(previous.tenant.as_deref(), id, reference);
+            }
+        }
+    }
+
+    pub fn get(&self, name: &str) -> Option<Arc<MohClass>> {
+        self.classes.read().unwrap().get(name).map(|(_, class)| class.clone())
+    }
+
+    /// Configuration of every class, by name
+    pub fn configs(&self) -> HashMap<String, MohClassConfig> {
+        self.classes
+            .read()
+            .unwrap()
+            .iter()
+            .map(|(name, (config, _))| (name.clone(), config.clone()))
+            .collect()
+    }
+
+    /// First of `names` that is a loaded class
+    pub fn find(&self, names: &[Option<&str>]) -> Option<Arc<MohClass>> {
+        names.iter().flatten().find_map(|name| self.get(name))
+    }
+
+    /// First of `names` that is a loaded class, else the `default` class
+    pub fn select(&self, names: &[Option<&str>]) -> Option<Arc<MohClass>> {
+        self.find(names).or_else(|| self.get(DEFAULT_MOH_CLASS))
+    }
+}
+
 /// Simple tone generator for MOH (as fallback)
 /// Generates a simple sine wave tone
 pub struct ToneGenerator {
@@ -207,6 +742,17 @@ mod tests {
         assert!(!player.is_playing().await);
     }
 
+    #[tokio::test]
+    async fn test_moh_close() {
+        let player = MohPlayer::new();
+        player.start().await.unwrap();
+
+        player.close().await;
+        player.close().await;
+        assert!(!player.is_playing().await);
+        assert!(player.start().await.is_err());
+    }
+
     #[tokio::test]
     async fn test_tone_generator() {
         let gen = ToneGenerator::default_tone();
@@ -257,4 +803,133 @@ mod tests {
         assert!(!player.config.loop_audio);
         assert_eq!(player.config.volume, 0.5);
     }
+
+    fn track(id: &str, samples: Vec<i16>) -> MohTrack {
+        MohTrack {
+            file_id: id.to_string(),
+            name: id.to_string(),
+            samples: Arc::new(samples),
+        }
+    }
+
+    fn class_of(tracks: Vec<MohTrack>, config: MohClassConfig, crossfade_ms: u32) -> Arc<MohClass> {
+        Arc::new(MohClass::new("test", tracks, &config, crossfade_ms))
+    }
+
+    #[test]
+    fn test_shuffle_is_deterministic_per_seed() {
+        let tracks: Vec<MohTrack> = (0..8).map(|i| track(&format!("t{}", i), vec![i; 10])).collect();
+        let shuffled = class_of(
+            tracks.clone(),
+            MohClassConfig {
+                shuffle: true,
+                ..Default::default()
+            },
+            0,
+        );
+        // Tracks of 10 samples without crossfade: every skip is one track
+        let played = |class: &Arc<MohClass>, seed: u64| {
+            let mut stream = PlaylistStream::new(class.clone(), seed);
+            (0..24)
+                .map(|_| {
+                    let id = stream.current_track().file_id.clone();
+                    stream.skip(10);
+                    id
+                })
+                .collect::<Vec<_>>()
+        };
+
+        let first = played(&shuffled, 7);
+        assert_eq!(first, played(&shuffled, 7));
+        assert_ne!(first, played(&shuffled, 8));
+        let all: Vec<String> = (0..8).map(|i| format!("t{}", i)).collect();
+        for pass in first.chunks(8) {
+            let mut pass = pass.to_vec();
+            pass.sort();
+            assert_eq!(pass, all);
+        }
+        // Every pass draws a new order
+        assert_ne!(first[..8], first[8..16]);
+
+        let ordered = class_of(tracks, MohClassConfig::default(), 0);
+        assert_eq!(played(&ordered, 7)[..16], [all.clone(), all].concat()[..]);
+    }
+
+    #[test]
+    fn test_crossfade_is_continuous_at_track_boundary() {
+        let class = class_of(
+            vec![track("a", vec![8000; 800]), track("b", vec![-8000; 800])],
+            MohClassConfig::default(),
+            20,
+        );
+        let mut stream = PlaylistStream::new(class, 1);
+        let mut samples = vec![0i16; 1400];
+        stream.read(&mut samples);
+
+        // 20 ms at 8 kHz: the last 160 samples of "a" fade into "b"
+        assert_eq!(samples[639], 8000);
+        assert!(samples[640] < 8000 && samples[640] > 7900);
+        assert!(samples[799] > -8000 && samples[799] < -7900);
+        // "b" goes on after the samples already mixed in
+        assert_eq!(samples[800], -8000);
+        let max_step = samples
+            .windows(2)
+            .map(|pair| (pair[1] as i32 - pair[0] as i32).abs())
+            .max()
+            .unwrap();
+        assert!(max_step <= 101, "step of {} between samples", max_step);
+        let playing = stream.now_playing();
+        assert_eq!(playing.file_id, "b");
+        assert_eq!(playing.position_ms, 95);
+
+        // Gain applies to every sample
+        let quiet = class_of(
+            vec![track("a", vec![1000; 100])],
+            MohClassConfig {
+                gain_db: -6.0206,
+                ..Default::default()
+            },
+            0,
+        );
+        let mut samples = vec![0i16; 10];
+        PlaylistStream::new(quiet, 1).read(&mut samples);
+        assert!(samples.iter().all(|s| *s == 500));
+    }
+
+    #[test]
+    fn test_resume_continues_where_the_last_call_left_off() {
+        let tracks = vec![track("a", vec![1; 800]), track("b", vec![2; 800])];
+        let continuing = class_of(
+            tracks.clone(),
+            MohClassConfig {
+                resume: MohResume::Continue,
+                ..Default::default()
+            },
+            0,
+        );
+        let mut first = PlaylistStream::new(continuing.clone(), 1);
+        first.skip(1200);
+        first.save_resume_point();
+        let next = PlaylistStream::new(continuing, 2).now_playing();
+        assert_eq!((next.file_id.as_str(), next.position_ms), ("b", 50));
+
+        let restarting = class_of(tracks, MohClassConfig::default(), 0);
+        let mut first = PlaylistStream::new(restarting.clone(), 1);
+        first.skip(1200);
+        first.save_resume_point();
+        let next = PlaylistStream::new(restarting, 2).now_playing();
+        assert_eq!((next.file_id.as_str(), next.position_ms), ("a", 0));
+    }
+
+    #[tokio::test]
+    async fn test_class_player_reports_track() {
+        let class = class_of(vec![track("a", vec![1; 8000]), track("b", vec![2; 8000])], MohClassConfig::default(), 0);
+        let player = MohPlayer::with_class(class, 3);
+        assert_eq!(player.source(), "class:test");
+        player.start().await.unwrap();
+        assert_eq!(player.now_playing().unwrap().file_id, "a");
+        assert_eq!(player.read_frame(160).unwrap(), vec![1; 160]);
+        assert!(MohPlayer::new().now_playing().is_none());
+        player.close().await;
+    }
 }
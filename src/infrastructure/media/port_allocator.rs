@@ -0,0 +1,274 @@
+//! RTP port allocation
+//!
+//! Hands out even RTP ports (RTCP on the odd port above) from a fixed range
+//! and takes them back when a stream is closed. Streams multiplexing RTCP
+//! on the RTP port (RFC 5761) take a single port instead, filling the
+//! other half of a slot another single port already uses before starting a
+//! new one, so pairs stay available for streams needing them. The
+//! allocator also counts
+//! the tasks spawned by streams holding its ports, so leaks show up as a
+//! non-zero count after every call is gone.
+
+use std::collections::HashSet;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use tracing::warn;
+
+/// Default first RTP port
+pub const DEFAULT_RTP_PORT_MIN: u16 = 10000;
+/// Default last RTP port
+pub const DEFAULT_RTP_PORT_MAX: u16 = 20000;
+
+struct AllocatorState {
+    next: u16,
+    /// Every reserved port, both halves of a pair
+    ports: HashSet<u16>,
+    /// RTP ports of the reserved pairs
+    pairs: HashSet<u16>,
+}
+
+/// RTP/RTCP port pair allocator
+pub struct RtpPortAllocator {
+    min: u16,
+    max: u16,
+    state: Mutex<AllocatorState>,
+    live_tasks: Arc<AtomicUsize>,
+}
+
+impl RtpPortAllocator {
+    /// Allocate pairs within `min..=max`; `max` is the highest port bound,
+    /// so the last pair's RTCP port is at most `max`
+    pub fn new(min: u16, max: u16) -> Self {
+        // RTP on even ports; an odd min of 65535 leaves no slot at all
+        let min = min.saturating_add(min % 2);
+        let max = max.max(min);
+        Self {
+            min,
+            max,
+            state: Mutex::new(AllocatorState {
+                next: min,
+                ports: HashSet::new(),
+                pairs: HashSet::new(),
+            }),
+            live_tasks: Arc::new(AtomicUsize::new(0)),
+        }
+    }
+
+    /// Number of pairs in the range
+    pub fn capacity(&self) -> usize {
+        if self.min % 2 == 1 || self.min >= self.max {
+            return 0;
+        }
+        ((self.max - 1 - self.min) / 2 + 1) as usize
+    }
+
+    /// Reserve the next free pair and return its RTP port
+    pub fn allocate(&self) -> Option<u16> {
+        let mut state = self.state.lock().unwrap();
+        let port = self.next_free_slot(&mut state)?;
+        state.ports.insert(port);
+        state.ports.insert(port + 1);
+        state.pairs.insert(port);
+        Some(port)
+    }
+
+    /// Reserve a single port, for a stream with RTCP multiplexed on it
+    ///
+    /// The free half of a slot holding another single port is used first,
+    /// then the even port of the next free pair.
+    pub fn allocate_single(&self) -> Option<u16> {
+        if self.capacity() == 0 {
+            return None;
+        }
+        let mut state = self.state.lock().unwrap();
+        let half_free = (self.min..self.max).step_by(2).find_map(|slot| {
+            match (state.ports.contains(&slot), state.ports.contains(&(slot + 1))) {
+                (true, false) if !state.pairs.contains(&slot) => Some(slot + 1),
+                (false, true) => Some(slot),
+                _ => None,
+            }
+        });
+        let port = match half_free {
+            Some(port) => port,
+            None => self.next_free_slot(&mut state)?,
+        };
+        state.ports.insert(port);
+        Some(port)
+    }
+
+    /// Next slot with both ports free, round robin from the last one
+    fn next_free_slot(&self, state: &mut AllocatorState) -> Option<u16> {
+        for _ in 0..self.capacity() {
+            let port = state.next;
+            state.next = match port.checked_add(2) {
+                Some(next) if next < self.max => next,
+                _ => self.min,
+            };
+            if !state.ports.contains(&port) && !state.ports.contains(&(port + 1)) {
+                return Some(port);
+            }
+        }
+        warn!("RTP port range {}-{} exhausted", self.min, self.max);
+        None
+    }
+
+    /// Return a pair, or a single port, to the pool
+    pub fn release(&self, port: u16) {
+        let mut state = self.state.lock().unwrap();
+        if state.pairs.remove(&port) {
+            state.ports.remove(&port);
+            state.ports.remove(&(port + 1));
+        } else if !state.ports.remove(&port) {
+            warn!("Released RTP port {} was not allocated", port);
+        }
+    }
+
+    /// Whether `port` is reserved, alone or as part of a pair
+    pub fn is_allocated(&self, port: u16) -> bool {
+        self.state.lock().unwrap().ports.contains(&port)
+    }
+
+    /// Number of pair slots with at least one port reserved
+    pub fn in_use(&self) -> usize {
+        let state = self.state.lock().unwrap();
+        let slots: HashSet<u16> = state.ports.iter().map(|port| port & !1).collect();
+        slots.len()
+    }
+
+    /// Tasks currently running for streams on this allocator's ports
+    pub fn live_tasks(&self) -> usize {
+        self.live_tasks.load(Ordering::SeqCst)
+    }
+
+    /// Count a task until the returned guard is dropped
+    pub(crate) fn track_task(&self) -> TaskTracker {
+        self.live_tasks.fetch_add(1, Ordering::SeqCst);
+        TaskTracker {
+            live_tasks: self.live_tasks.clone(),
+        }
+    }
+}
+
+impl Default for RtpPortAllocator {
+    fn default() -> Self {
+        Self::new(DEFAULT_RTP_PORT_MIN, DEFAULT_RTP_PORT_MAX)
+    }
+}
+
+/// A reserved port (or pair), returned to the allocator when dropped
+///
+/// Owned by the socket bound to the port, so the port only becomes
+/// allocatable again once the socket is closed.
+pub(crate) struct PortLease {
+    allocator: Arc<RtpPortAllocator>,
+    port: u16,
+}
+
+impl PortLease {
+    pub(crate) fn new(allocator: Arc<RtpPortAllocator>, port: u16) -> Self {
+        Self { allocator, port }
+    }
+}
+
+impl Drop for PortLease {
+    fn drop(&mut self) {
+        self.allocator.release(self.port);
+    }
+}
+
+/// Held by a media task; dropped when the task finishes or is aborted
+pub(crate) struct TaskTracker {
+    live_tasks: Arc<AtomicUsize>,
+}
+
+impl Drop for TaskTracker {
+    fn drop(&mut self) {
+        self.live_tasks.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_allocates_even_pairs_and_reuses_released() {
+        let allocator = RtpPortAllocator::new(30001, 30007);
+        assert_eq!(allocator.capacity(), 3);
+
+        assert_eq!(allocator.allocate(), Some(30002));
+        assert_eq!(allocator.allocate(), Some(30004));
+        assert_eq!(allocator.allocate(), Some(30006));
+        assert_eq!(allocator.allocate(), None);
+        assert_eq!(allocator.in_use(), 3);
+
+        allocator.release(30004);
+        assert!(!allocator.is_allocated(30004));
+        assert_eq!(allocator.allocate(), Some(30004));
+    }
+
+    #[test]
+    fn test_single_ports_fill_half_used_slots() {
+        let allocator = RtpPortAllocator::new(30000, 30005);
+
+        assert_eq!(allocator.allocate_single(), Some(30000));
+        assert_eq!(allocator.allocate_single(), Some(30001));
+        assert_eq!(allocator.allocate(), Some(30002));
+        assert_eq!(allocator.allocate_single(), Some(30004));
+        assert_eq!(allocator.in_use(), 3);
+        // The last slot is half used: no pair left, one single port
+        assert_eq!(allocator.allocate(), None);
+        assert!(!allocator.is_allocated(30005));
+
+        allocator.release(30002);
+        assert!(!allocator.is_allocated(30003));
+        allocator.release(30004);
+        assert_eq!(allocator.allocate(), Some(30002));
+        assert_eq!(allocator.allocate_single(), Some(30004));
+    }
+
+    #[test]
+    fn test_range_at_top_of_port_space() {
+        let allocator = RtpPortAllocator::new(65535, 65535);
+        assert_eq!(allocator.capacity(), 0);
+        assert_eq!(allocator.allocate(), None);
+        assert_eq!(allocator.allocate_single(), None);
+
+        let allocator = RtpPortAllocator::new(65533, 65535);
+        assert_eq!(allocator.allocate(), Some(65534));
+        assert_eq!(allocator.allocate(), None);
+
+        let allocator = RtpPortAllocator::new(65534, 65534);
+        assert_eq!(allocator.capacity(), 0);
+        assert_eq!(allocator.allocate(), None);
+    }
+
+    #[test]
+    fn test_no_port_above_max_is_handed_out() {
+        let allocator = RtpPortAllocator::new(40000, 40006);
+        assert_eq!(allocator.capacity(), 3);
+
+        let pairs: Vec<u16> = std::iter::from_fn(|| allocator.allocate()).collect();
+        assert_eq!(pairs, vec![40000, 40002, 40004]);
+        assert!(!allocator.is_allocated(40006));
+        assert_eq!(allocator.allocate_single(), None);
+
+        allocator.release(40004);
+        assert_eq!(allocator.allocate_single(), Some(40004));
+        assert_eq!(allocator.allocate_single(), Some(40005));
+        assert_eq!(allocator.allocate_single(), None);
+        let highest = (40000..=40010).filter(|port| allocator.is_allocated(*port)).max();
+        assert_eq!(highest, Some(40005));
+    }
+
+    #[test]
+    fn test_task_tracker_counts_live_tasks() {
+        let allocator = RtpPortAllocator::default();
+        let first = allocator.track_task();
+        let second = allocator.track_task();
+        assert_eq!(allocator.live_tasks(), 2);
+        drop(first);
+        drop(second);
+        assert_eq!(allocator.live_tasks(), 0);
+    }
+}
@@ -0,0 +1,248 @@
+//! Packet time (ptime) adaptation
+//!
+//! Endpoints may negotiate different packet times (e.g. 40ms on one leg,
+//! 20ms on the other). Forwarding packets unchanged makes the receiver's
+//! jitter buffer stutter, so the bridge re-packetizes each direction to
+//! the packet time of the leg it sends to:
+//! - same codec: payload bytes are regrouped as-is (repack only)
+//! - PCMU <-> PCMA: frames are decoded to PCM and re-encoded
+//!
+//! Only G.711 can be split on sample boundaries, so other codecs are not
+//! adapted.
+
+use super::codec::G711Type;
+use super::rtp::RtpPacket;
+use bytes::{Bytes, BytesMut};
+use serde::{Deserialize, Serialize};
+
+/// Packet time offered, and assumed when the SDP has no a=ptime
+pub const DEFAULT_PTIME_MS: u32 = 20;
+
+/// G.711 clock rate
+const SAMPLE_RATE: u32 = 8000;
+
+/// Codec and packet time of a leg
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PacketFormat {
+    pub payload_type: u8,
+    pub ptime_ms: u32,
+}
+
+impl PacketFormat {
+    pub fn new(payload_type: u8, ptime_ms: u32) -> Self {
+        Self {
+            payload_type,
+            ptime_ms,
+        }
+    }
+
+    /// Samples (and RTP timestamp units) per packet
+    pub fn samples_per_packet(&self) -> usize {
+        (SAMPLE_RATE * self.ptime_ms / 1000) as usize
+    }
+}
+
+/// Audio received but not yet sent
+enum Pending {
+    /// Payload of the target codec
+    Payload(BytesMut),
+    /// Decoded audio, encoded with `target` when sent
+    Pcm {
+        source: G711Type,
+        target: G711Type,
+        samples: Vec<i16>,
+    },
+}
+
+impl Pending {
+    fn len(&self) -> usize {
+        match self {
+            Pending::Payload(payload) => payload.len(),
+            Pending::Pcm { samples, .. } => samples.len(),
+        }
+    }
+
+    fn push(&mut self, payload: &[u8]) {
+        match self {
+            Pending::Payload(pending) => pending.extend_from_slice(payload),
+            Pending::Pcm {
+                source, samples, ..
+            } => samples.extend(source.decode(payload)),
+        }
+    }
+
+    /// First `count` samples, encoded for the target leg
+    fn take(&mut self, count: usize) -> Bytes {
+        match self {
+            Pending::Payload(pending) => pending.split_to(count).freeze(),
+            Pending::Pcm {
+                target, samples, ..
+            } => {
+                let frame: Vec<i16> = samples.drain(..count).collect();
+                target.encode(&frame)
+            }
+        }
+    }
+}
+
+/// Re-packetizes one direction of a bridge to the target leg's packet time
+///
+/// Output timestamps advance by exactly one packet's samples; a gap in the
+/// input timestamps (lost packets, silence suppression) sends what is
+/// pending and restarts from the new timestamp.
+pub struct PtimeAdapter {
+    source: PacketFormat,
+    target: PacketFormat,
+    /// SSRC of the target leg
+    ssrc: u32,
+    pending: Pending,
+    /// Timestamp of the first pending sample
+    timestamp: Option<u32>,
+    /// Timestamp the next input packet should carry
+    next_input: Option<u32>,
+    sequence: Option<u16>,
+    marker: bool,
+}
+
+impl PtimeAdapter {
+    /// Adapter from `source` to `target`, or None when either is not G.711
+    pub fn new(source: PacketFormat, target: PacketFormat, ssrc: u32) -> Option<Self> {
+        let from = G711Type::from_payload_type(source.payload_type)?;
+        let to = G711Type::from_payload_type(target.payload_type)?;
+        if target.samples_per_packet() == 0 {
+            return None;
+        }
+
+        let pending = if from == to {
+            Pending::Payload(BytesMut::new())
+        } else {
+            Pending::Pcm {
+                source: from,
+                target: to,
+                samples: Vec::new(),
+            }
+        };
+
+        Some(Self {
+            source,
+            target,
+            ssrc,
+            pending,
+            timestamp: None,
+            next_input: None,
+            sequence: None,
+            marker: false,
+        })
+    }
+
+    /// Whether payloads are transcoded rather than only regrouped
+    pub fn is_transcoding(&self) -> bool {
+        matches!(self.pending, Pending::Pcm { .. })
+    }
+
+    /// Packets for the target leg, for one packet received from the source
+    ///
+    /// Packets of another payload type (telephone-event, comfort noise)
+    /// are passed through.
+    pub fn push(&mut self, packet: &RtpPacket) -> Vec<RtpPacket> {
+        let mut out = Vec::new();
+
+        if packet.payload_type != self.source.payload_type {
+            let sequence = self.next_sequence(packet.sequence);
+            let mut passthrough = packet.clone();
+            passthrough.sequence = sequence;
+            passthrough.ssrc = self.ssrc;
+            out.push(passthrough);
+            return out;
+        }
+
+        if self
+            .next_input
+            .is_some_and(|expected| expected != packet.timestamp)
+        {
+            out.extend(self.flush());
+        }
+        if self.timestamp.is_none() {
+            self.timestamp = Some(packet.timestamp);
+        }
+        self.marker |= packet.marker;
+        self.pending.push(&packet.payload);
+        self.next_input = Some(packet.timestamp.wrapping_add(packet.payload.len() as u32));
+
+        let frame = self.target.samples_per_packet();
+        while self.pending.len() >= frame {
+            out.push(self.emit(frame, packet.sequence));
+        }
+        out
+    }
+
+    /// Send what is pending as a short packet
+    pub fn flush(&mut self) -> Option<RtpPacket> {
+        let remaining = self.pending.len();
+        let packet = (remaining > 0).then(|| self.emit(remaining, 0));
+        self.timestamp = None;
+        self.next_input = None;
+        packet
+    }
+
+    fn emit(&mut self, samples: usize, input_sequence: u16) -> RtpPacket {
+        let timestamp = self.timestamp.unwrap_or_default();
+        let sequence = self.next_sequence(input_sequence);
+        let mut packet = RtpPacket::new(
+            self.target.payload_type,
+            sequence,
+            timestamp,
+            self.ssrc,
+            self.pending.take(samples),
+        );
+        packet.set_marker(std::mem::take(&mut self.marker));
+        self.timestamp = Some(timestamp.wrapping_add(samples as u32));
+        packet
+    }
+
+    /// Output sequence numbers start at the first input packet's
+    fn next_sequence(&mut self, input_sequence: u16) -> u16 {
+        let sequence = self.sequence.unwrap_or(input_sequence);
+        self.sequence = Some(sequence.wrapping_add(1));
+        sequence
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::infrastructure::media::codec::PcmuCodec;
+
+    #[test]
+    fn test_transcodes_and_restarts_after_gap() {
+        // PCMU 30ms -> PCMA 20ms
+        let mut adapter =
+            PtimeAdapter::new(PacketFormat::new(0, 30), PacketFormat::new(8, 20), 7).unwrap();
+        assert!(adapter.is_transcoding());
+
+        let tone: Vec<i16> = (0..240).map(|i| ((i % 40) as i16 - 20) * 500).collect();
+        let payload = PcmuCodec::encode(&tone);
+
+        let first = adapter.push(&RtpPacket::new(0, 10, 1000, 1, payload.clone()));
+        assert_eq!(first.len(), 1);
+        assert_eq!(first[0].payload_type, 8);
+        assert_eq!(first[0].payload.len(), 160);
+        assert_eq!(first[0].timestamp, 1000);
+        assert_eq!(first[0].ssrc, 7);
+
+        let second = adapter.push(&RtpPacket::new(0, 11, 1240, 1, payload.clone()));
+        assert_eq!(second.len(), 2);
+        assert_eq!(second[0].timestamp, 1160);
+        assert_eq!(second[1].timestamp, 1320);
+        assert_eq!(second[1].sequence, 12);
+
+        // One packet lost: output timing restarts at the new timestamp
+        let third = adapter.push(&RtpPacket::new(0, 13, 1720, 1, payload));
+        assert_eq!(third.len(), 1);
+        assert_eq!(third[0].timestamp, 1720);
+        assert_eq!(adapter.flush().unwrap().payload.len(), 80);
+
+        // Non-G.711 is not adapted
+        assert!(PtimeAdapter::new(PacketFormat::new(9, 30), PacketFormat::new(0, 20), 7).is_none());
+    }
+}
@@ -0,0 +1,197 @@
+//! RTP relay between two call legs
+//!
+//! Each leg has its own UDP socket bound in that leg's address family, so a
+//! call can bridge an IPv4 phone to an IPv6 phone. Packets received on one
+//! leg are forwarded unchanged to the other leg's remote address. A leg's
+//! remote is learned from its first packet (symmetric RTP) unless set from
+//! the SDP beforehand.
+
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use tokio::net::UdpSocket;
+use tokio::sync::RwLock;
+use tokio::task::JoinHandle;
+use tracing::{debug, info, warn};
+
+/// One side of a relayed call
+struct RelayLeg {
+    socket: Arc<UdpSocket>,
+    remote: Arc<RwLock<Option<SocketAddr>>>,
+}
+
+impl RelayLeg {
+    async fn bind(addr: SocketAddr) -> Result<Self, std::io::Error> {
+        let socket = UdpSocket::bind(addr).await?;
+        info!("Relay leg bound to {}", socket.local_addr()?);
+        Ok(Self {
+            socket: Arc::new(socket),
+            remote: Arc::new(RwLock::new(None)),
+        })
+    }
+}
+
+/// Media relay
+///
+/// Forwards RTP between leg A (caller) and leg B (callee)
+pub struct MediaRelay {
+    leg_a: RelayLeg,
+    leg_b: RelayLeg,
+    relayed: Arc<AtomicU64>,
+    tasks: Mutex<Vec<JoinHandle<()>>>,
+}
+
+impl MediaRelay {
+    /// Bind the relay sockets for both legs
+    ///
+    /// Pass `0.0.0.0:port` or `[::]:port` according to each leg's family.
+    pub async fn bind(leg_a: SocketAddr, leg_b: SocketAddr) -> Result<Self, std::io::Error> {
+        Ok(Self {
+            leg_a: RelayLeg::bind(leg_a).await?,
+            leg_b: RelayLeg::bind(leg_b).await?,
+            relayed: Arc::new(AtomicU64::new(0)),
+            tasks: Mutex::new(Vec::new()),
+        })
+    }
+
+    /// Local address leg A sends media to
+    pub fn local_addr_a(&self) -> Result<SocketAddr, std::io::Error> {
+        self.leg_a.socket.local_addr()
+    }
+
+    /// Local address leg B sends media to
+    pub fn local_addr_b(&self) -> Result<SocketAddr, std::io::Error> {
+        self.leg_b.socket.local_addr()
+    }
+
+    /// Media address of leg A (from its SDP)
+    pub async fn set_remote_a(&self, addr: SocketAddr) {
+        *self.leg_a.remote.write().await = Some(addr);
+    }
+
+    /// Media address of leg B (from its SDP)
+    pub async fn set_remote_b(&self, addr: SocketAddr) {
+        *self.leg_b.remote.write().await = Some(addr);
+    }
+
+    /// Number of packets forwarded in either direction
+    pub fn packets_relayed(&self) -> u64 {
+        self.relayed.load(Ordering::Relaxed)
+    }
+
+    /// Start forwarding in both directions
+    pub fn start(&self) {
+        let mut tasks = self.tasks.lock().unwrap();
+        if !tasks.is_empty() {
+            return;
+        }
+        tasks.push(self.spawn_forwarder(&self.leg_a, &self.leg_b, "A->B"));
+        tasks.push(self.spawn_forwarder(&self.leg_b, &self.leg_a, "B->A"));
+        info!("Media relay started");
+    }
+
+    /// Stop forwarding
+    pub fn stop(&self) {
+        for task in self.tasks.lock().unwrap().drain(..) {
+            task.abort();
+        }
+        info!("Media relay stopped");
+    }
+
+    fn spawn_forwarder(&self, from: &RelayLeg, to: &RelayLeg, label: &'static str) -> JoinHandle<()> {
+        let in_socket = from.socket.clone();
+        let in_remote = from.remote.clone();
+        let out_socket = to.socket.clone();
+        let out_remote = to.remote.clone();
+        let relayed = self.relayed.clone();
+
+        tokio::spawn(async move {
+            let mut buf = vec![0u8; 2048];
+            loop {
+                let (len, source) = match in_socket.recv_from(&mut buf).await {
+                    Ok(received) => received,
+                    Err(e) => {
+                        warn!("Relay {} receive error: {}", label, e);
+                        continue;
+                    }
+                };
+
+                // Latch onto the sender when no remote is known yet
+                {
+                    let mut remote = in_remote.write().await;
+                    if remote.is_none() {
+                        debug!("Relay {} learned remote {}", label, source);
+                        *remote = Some(source);
+                    }
+                }
+
+                let destination = *out_remote.read().await;
+                match destination {
+                    Some(destination) => {
+                        if let Err(e) = out_socket.send_to(&buf[..len], destination).await {
+                            warn!("Relay {} send to {} failed: {}", label, destination, e);
+                        } else {
+                            relayed.fetch_add(1, Ordering::Relaxed);
+                        }
+                    }
+                    None => debug!("Relay {} dropping packet: other leg unknown", label),
+                }
+            }
+        })
+    }
+}
+
+impl Drop for MediaRelay {
+    fn drop(&mut self) {
+        self.stop();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    async fn recv(socket: &UdpSocket) -> (Vec<u8>, SocketAddr) {
+        let mut buf = vec![0u8; 2048];
+        let (len, from) = tokio::time::timeout(Duration::from_secs(2), socket.recv_from(&mut buf))
+            .await
+            .expect("relay did not forward packet")
+            .unwrap();
+        buf.truncate(len);
+        (buf, from)
+    }
+
+    #[tokio::test]
+    async fn test_relay_bridges_ipv4_and_ipv6_legs() {
+        let relay = MediaRelay::bind("127.0.0.1:0".parse().unwrap(), "[::1]:0".parse().unwrap())
+            .await
+            .unwrap();
+        relay.start();
+
+        let phone_v4 = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let phone_v6 = UdpSocket::bind("[::1]:0").await.unwrap();
+        relay.set_remote_b(phone_v6.local_addr().unwrap()).await;
+
+        // v4 -> v6
+        phone_v4
+            .send_to(b"rtp-from-v4", relay.local_addr_a().unwrap())
+            .await
+            .unwrap();
+        let (data, from) = recv(&phone_v6).await;
+        assert_eq!(data, b"rtp-from-v4");
+        assert_eq!(from, relay.local_addr_b().unwrap());
+
+        // v6 -> v4, to the learned address of the v4 phone
+        phone_v6
+            .send_to(b"rtp-from-v6", relay.local_addr_b().unwrap())
+            .await
+            .unwrap();
+        let (data, from) = recv(&phone_v4).await;
+        assert_eq!(data, b"rtp-from-v6");
+        assert_eq!(from, relay.local_addr_a().unwrap());
+
+        assert_eq!(relay.packets_relayed(), 2);
+        relay.stop();
+    }
+}
@@ -0,0 +1,439 @@
+//! Ringback toward a caller while the callee leg alerts
+//!
+//! Some callees and trunks play their own early media (announcements,
+//! custom ringback) in a 183 with SDP; that is relayed to the caller. Others
+//! only send 180, and the caller hears ringback only if we generate it, in
+//! the cadence callers of the tenant's country expect. The policy picks the
+//! source, per trunk and per tenant.
+
+use super::codec::G711Type;
+use super::moh::ToneGenerator;
+use super::stream::MediaStream;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::task::JoinHandle;
+use tracing::{debug, info, warn};
+
+/// Sample rate of generated ringback
+const SAMPLE_RATE: u32 = 8000;
+/// Samples per 20 ms packet
+const FRAME_SAMPLES: usize = 160;
+
+/// National ringback tone: frequencies and on/off cadence
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TonePlan {
+    /// 440 + 480 Hz, 2 s on, 4 s off
+    #[default]
+    Us,
+    /// 400 + 450 Hz, double ring: 0.4 s on, 0.2 s off, 0.4 s on, 2 s off
+    Uk,
+    /// 425 Hz, 1 s on, 4 s off (ETSI)
+    Eu,
+}
+
+impl TonePlan {
+    /// Frequencies mixed while the tone is on, in Hz
+    pub fn frequencies(&self) -> &'static [f32] {
+        match self {
+            TonePlan::Us => &[440.0, 480.0],
+            TonePlan::Uk => &[400.0, 450.0],
+            TonePlan::Eu => &[425.0],
+        }
+    }
+
+    /// Repeating (on, off) periods, in milliseconds
+    pub fn cadence(&self) -> &'static [(u32, u32)] {
+        match self {
+            TonePlan::Us => &[(2000, 4000)],
+            TonePlan::Uk => &[(400, 200), (400, 2000)],
+            TonePlan::Eu => &[(1000, 4000)],
+        }
+    }
+
+    /// Whether the tone is on `ms` milliseconds into its cadence
+    pub fn is_on_at(&self, ms: u64) -> bool {
+        let cycle: u64 = self
+            .cadence()
+            .iter()
+            .map(|(on, off)| (on + off) as u64)
+            .sum();
+        let mut offset = ms % cycle;
+        for (on, off) in self.cadence() {
+            if offset < *on as u64 {
+                return true;
+            }
+            if offset < (on + off) as u64 {
+                return false;
+            }
+            offset -= (on + off) as u64;
+        }
+        false
+    }
+}
+
+/// Where the caller's audio comes from while the callee leg alerts
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum RingbackPolicy {
+    /// Relay the callee's early media when it sends any, else play
+    /// ringback locally
+    #[default]
+    PreferRemote,
+    /// Always play local ringback, ignoring the callee's early media
+    AlwaysLocal,
+    /// Never play local ringback: relay early media, else leave ringback
+    /// to the caller's phone
+    NeverLocal,
+}
+
+/// Ringback settings of a trunk or tenant; unset fields fall through
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct RingbackSettings {
+    pub policy: Option<RingbackPolicy>,
+    pub tone_plan: Option<TonePlan>,
+}
+
+/// Ringback configuration
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct RingbackConfig {
+    pub policy: RingbackPolicy,
+    pub tone_plan: TonePlan,
+    /// Per-tenant settings, keyed by SIP realm
+    pub tenants: HashMap<String, RingbackSettings>,
+    /// Per-trunk settings, keyed by trunk name; these win over the tenant's
+    pub trunks: HashMap<String, RingbackSettings>,
+}
+
+impl RingbackConfig {
+    /// Policy and tone plan of a call through `trunk` from tenant `realm`
+    pub fn resolve(&self, trunk: Option<&str>, realm: Option<&str>) -> (RingbackPolicy, TonePlan) {
+        let layers = [
+            trunk.and_then(|trunk| self.trunks.get(trunk)),
+            realm.and_then(|realm| self.tenants.get(realm)),
+        ];
+        let policy = layers
+            .iter()
+            .flatten()
+            .find_map(|settings| settings.policy)
+            .unwrap_or(self.policy);
+        let tone_plan = layers
+            .iter()
+            .flatten()
+            .find_map(|settings| settings.tone_plan)
+            .unwrap_or(self.tone_plan);
+        (policy, tone_plan)
+    }
+}
+
+/// Audio feeding the caller leg
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case", tag = "source", content = "tone_plan")]
+pub enum RingbackSource {
+    Silence,
+    /// Ringback generated here
+    LocalTone(TonePlan),
+    /// The callee leg's early media, relayed
+    EarlyMedia,
+}
+
+impl RingbackSource {
+    /// Provisional status to send the caller for `received` from the
+    /// callee: 183 whenever the caller gets early media of either kind
+    pub fn caller_status(&self, received: u16) -> u16 {
+        match self {
+            RingbackSource::Silence => received,
+            RingbackSource::LocalTone(_) | RingbackSource::EarlyMedia => 183,
+        }
+    }
+}
+
+/// Ringback of one call, following the callee leg's provisional responses
+#[derive(Debug, Clone)]
+pub struct Ringback {
+    policy: RingbackPolicy,
+    tone_plan: TonePlan,
+    source: RingbackSource,
+}
+
+impl Ringback {
+    pub fn new(policy: RingbackPolicy, tone_plan: TonePlan) -> Self {
+        Self {
+            policy,
+            tone_plan,
+            source: RingbackSource::Silence,
+        }
+    }
+
+    pub fn source(&self) -> RingbackSource {
+        self.source
+    }
+
+    /// Source after a provisional response from the callee leg; a 183 with
+    /// SDP carries early media
+    pub fn on_provisional(&mut self, status: u16, early_media: bool) -> RingbackSource {
+        if !(180..200).contains(&status) {
+            return self.source;
+        }
+        let local = RingbackSource::LocalTone(self.tone_plan);
+        self.source = match (self.policy, early_media) {
+            (RingbackPolicy::AlwaysLocal, _) => local,
+            (_, true) => RingbackSource::EarlyMedia,
+            // Once relayed, early media keeps playing through later 180s
+            (_, false) if self.source == RingbackSource::EarlyMedia => self.source,
+            (RingbackPolicy::PreferRemote, false) => local,
+            (RingbackPolicy::NeverLocal, false) => RingbackSource::Silence,
+        };
+        self.source
+    }
+}
+
+/// Ringback tone generator, following its plan's cadence
+pub struct RingbackTone {
+    plan: TonePlan,
+    tones: Vec<ToneGenerator>,
+    /// Samples generated so far
+    position: AtomicU64,
+}
+
+impl RingbackTone {
+    pub fn new(plan: TonePlan) -> Self {
+        let amplitude = 0.3 / plan.frequencies().len() as f32;
+        Self {
+            plan,
+            tones: plan
+                .frequencies()
+                .iter()
+                .map(|frequency| ToneGenerator::new(*frequency, SAMPLE_RATE, amplitude))
+                .collect(),
+            position: AtomicU64::new(0),
+        }
+    }
+
+    pub fn plan(&self) -> TonePlan {
+        self.plan
+    }
+
+    /// Next `count` samples: the mixed tones while on, silence while off
+    pub async fn next_samples(&self, count: usize) -> Vec<i16> {
+        let start = self.position.fetch_add(count as u64, Ordering::SeqCst);
+        let mut samples = Vec::with_capacity(count);
+        for n in start..start + count as u64 {
+            let mut sample = 0i32;
+            for tone in &self.tones {
+                sample += tone.next_sample().await as i32;
+            }
+            let on = self.plan.is_on_at(n * 1000 / SAMPLE_RATE as u64);
+            samples.push(if on { sample as i16 } else { 0 });
+        }
+        samples
+    }
+}
+
+/// Plays local ringback toward a caller's stream
+pub struct RingbackPlayer {
+    tone: Arc<RingbackTone>,
+    codec: G711Type,
+    task: Mutex<Option<JoinHandle<()>>>,
+    /// Set once by `close()`
+    closed: AtomicBool,
+}
+
+impl RingbackPlayer {
+    /// Player encoding with the caller's payload type (PCMU unless PCMA)
+    pub fn new(plan: TonePlan, payload_type: u8) -> Self {
+        Self {
+            tone: Arc::new(RingbackTone::new(plan)),
+            codec: G711Type::from_payload_type(payload_type).unwrap_or(G711Type::PCMU),
+            task: Mutex::new(None),
+            closed: AtomicBool::new(false),
+        }
+    }
+
+    pub fn plan(&self) -> TonePlan {
+        self.tone.plan()
+    }
+
+    /// Send ringback on `stream` in 20 ms packets until closed
+    pub fn start(&self, stream: Arc<MediaStream>) -> Result<(), String> {
+        if self.closed.load(Ordering::SeqCst) {
+            return Err("Ringback player is closed".to_string());
+        }
+        let mut task = self.task.lock().unwrap();
+        if task.is_some() {
+            return Err("Ringback is already playing".to_string());
+        }
+
+        let tone = self.tone.clone();
+        let codec = self.codec;
+        *task = Some(tokio::spawn(async move {
+            let mut interval = tokio::time::interval(Duration::from_millis(20));
+            let mut timestamp = 0u32;
+            let mut marker = true;
+            loop {
+                interval.tick().await;
+                let samples = tone.next_samples(FRAME_SAMPLES).await;
+                if let Err(e) = stream
+                    .send_rtp(codec.encode(&samples), timestamp, marker)
+                    .await
+                {
+                    warn!("Failed to send ringback: {}", e);
+                    break;
+                }
+                timestamp = timestamp.wrapping_add(FRAME_SAMPLES as u32);
+                marker = false;
+            }
+        }));
+        info!("Playing {:?} ringback", self.tone.plan());
+        Ok(())
+    }
+
+    /// Whether ringback is being sent
+    pub fn is_playing(&self) -> bool {
+        self.task
+            .lock()
+            .unwrap()
+            .as_ref()
+            .is_some_and(|task| !task.is_finished())
+    }
+
+    /// Stop ringback for good
+    ///
+    /// Safe to call more than once; a closed player cannot be restarted.
+    pub fn close(&self) {
+        if self.closed.swap(true, Ordering::SeqCst) {
+            return;
+        }
+        if let Some(task) = self.task.lock().unwrap().take() {
+            task.abort();
+        }
+        debug!("Ringback player closed");
+    }
+
+    /// Whether `close()` was called
+    pub fn is_closed(&self) -> bool {
+        self.closed.load(Ordering::SeqCst)
+    }
+}
+
+impl Drop for RingbackPlayer {
+    fn drop(&mut self) {
+        if let Some(task) = self.task.lock().unwrap().take() {
+            task.abort();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_tone_plan_cadences() {
+        assert!(TonePlan::Us.is_on_at(0));
+        assert!(TonePlan::Us.is_on_at(1999));
+        assert!(!TonePlan::Us.is_on_at(2000));
+        assert!(TonePlan::Us.is_on_at(6000));
+
+        // UK double ring
+        assert!(TonePlan::Uk.is_on_at(100));
+        assert!(!TonePlan::Uk.is_on_at(500));
+        assert!(TonePlan::Uk.is_on_at(700));
+        assert!(!TonePlan::Uk.is_on_at(1500));
+        assert!(TonePlan::Uk.is_on_at(3000));
+
+        assert!(TonePlan::Eu.is_on_at(999));
+        assert!(!TonePlan::Eu.is_on_at(1000));
+        assert!(TonePlan::Eu.is_on_at(5000));
+    }
+
+    #[tokio::test]
+    async fn test_ringback_tone_is_silent_between_rings() {
+        let tone = RingbackTone::new(TonePlan::Eu);
+
+        // 1 s of tone, then silence
+        let ring = tone.next_samples(8000).await;
+        assert!(ring.iter().any(|s| *s != 0));
+        let pause = tone.next_samples(8000).await;
+        assert!(pause.iter().all(|s| *s == 0));
+    }
+
+    #[test]
+    fn test_ringback_follows_policy() {
+        let mut prefer_remote = Ringback::new(RingbackPolicy::PreferRemote, TonePlan::Uk);
+        assert_eq!(
+            prefer_remote.on_provisional(100, false),
+            RingbackSource::Silence
+        );
+        assert_eq!(
+            prefer_remote.on_provisional(180, false),
+            RingbackSource::LocalTone(TonePlan::Uk)
+        );
+        assert_eq!(
+            prefer_remote.on_provisional(183, true),
+            RingbackSource::EarlyMedia
+        );
+        assert_eq!(
+            prefer_remote.on_provisional(180, false),
+            RingbackSource::EarlyMedia
+        );
+
+        let mut always_local = Ringback::new(RingbackPolicy::AlwaysLocal, TonePlan::Us);
+        assert_eq!(
+            always_local.on_provisional(183, true),
+            RingbackSource::LocalTone(TonePlan::Us)
+        );
+
+        let mut never_local = Ringback::new(RingbackPolicy::NeverLocal, TonePlan::Us);
+        assert_eq!(
+            never_local.on_provisional(180, false),
+            RingbackSource::Silence
+        );
+        assert_eq!(
+            never_local.on_provisional(183, true),
+            RingbackSource::EarlyMedia
+        );
+        assert_eq!(RingbackSource::Silence.caller_status(180), 180);
+        assert_eq!(
+            RingbackSource::LocalTone(TonePlan::Us).caller_status(180),
+            183
+        );
+    }
+
+    #[test]
+    fn test_trunk_settings_win_over_tenant() {
+        let mut config = RingbackConfig::default();
+        config.tenants.insert(
+            "acme.example.com".to_string(),
+            RingbackSettings {
+                policy: Some(RingbackPolicy::NeverLocal),
+                tone_plan: Some(TonePlan::Uk),
+            },
+        );
+        config.trunks.insert(
+            "carrier".to_string(),
+            RingbackSettings {
+                policy: Some(RingbackPolicy::AlwaysLocal),
+                tone_plan: None,
+            },
+        );
+
+        assert_eq!(
+            config.resolve(Some("carrier"), Some("acme.example.com")),
+            (RingbackPolicy::AlwaysLocal, TonePlan::Uk)
+        );
+        assert_eq!(
+            config.resolve(None, Some("acme.example.com")),
+            (RingbackPolicy::NeverLocal, TonePlan::Uk)
+        );
+        assert_eq!(
+            config.resolve(Some("other"), None),
+            (RingbackPolicy::PreferRemote, TonePlan::Us)
+        );
+    }
+}
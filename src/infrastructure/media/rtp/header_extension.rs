@@ -0,0 +1,231 @@
+//! RTP header extensions (RFC 8285) and the client-to-mixer audio level
+//! (RFC 6464)
+//!
+//! [`RtpPacket`](super::RtpPacket) keeps the extension block as received, so
+//! packets with extensions we do not know pass through bridges untouched;
+//! the elements are parsed from it on demand.
+
+use bytes::{BufMut, Bytes, BytesMut};
+
+/// `a=extmap` URI of the client-to-mixer audio level extension
+pub const AUDIO_LEVEL_URI: &str = "urn:ietf:params:rtp-hdrext:ssrc-audio-level";
+
+/// Extension block profile of the one-byte header form
+pub const ONE_BYTE_PROFILE: u16 = 0xBEDE;
+
+/// Extension block profile of the two-byte header form (low 4 bits are
+/// application bits)
+pub const TWO_BYTE_PROFILE: u16 = 0x1000;
+
+/// One extension element
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HeaderExtension {
+    pub id: u8,
+    pub data: Bytes,
+}
+
+impl HeaderExtension {
+    pub fn new(id: u8, data: Bytes) -> Self {
+        Self { id, data }
+    }
+
+    /// Whether the element fits the one-byte header form
+    fn fits_one_byte(&self) -> bool {
+        (1..=14).contains(&self.id) && (1..=16).contains(&self.data.len())
+    }
+}
+
+/// Elements of an extension block, `None` when the block is not in one of
+/// the RFC 8285 forms (or is malformed)
+pub fn parse_extensions(profile: u16, data: &[u8]) -> Option<Vec<HeaderExtension>> {
+    let two_byte = if profile == ONE_BYTE_PROFILE {
+        false
+    } else if profile & 0xFFF0 == TWO_BYTE_PROFILE {
+        true
+    } else {
+        return None;
+    };
+
+    let mut elements = Vec::new();
+    let mut i = 0;
+    while i < data.len() {
+        // Padding between elements
+        if data[i] == 0 {
+            i += 1;
+            continue;
+        }
+        let (id, len, header_len) = if two_byte {
+            let len = *data.get(i + 1)? as usize;
+            (data[i], len, 2)
+        } else {
+            let id = data[i] >> 4;
+            // ID 15 ends the block
+            if id == 15 {
+                break;
+            }
+            (id, (data[i] & 0x0F) as usize + 1, 1)
+        };
+        let start = i + header_len;
+        let end = start + len;
+        if end > data.len() {
+            return None;
+        }
+        elements.push(HeaderExtension::new(id, Bytes::copy_from_slice(&data[start..end])));
+        i = end;
+    }
+    Some(elements)
+}
+
+/// Extension block (profile and data padded to 32-bit words) carrying
+/// `elements`, in the one-byte form when they all fit it
+pub fn serialize_extensions(elements: &[HeaderExtension]) -> (u16, Bytes) {
+    let one_byte = elements.iter().all(HeaderExtension::fits_one_byte);
+    let mut buf = BytesMut::new();
+    for element in elements {
+        if one_byte {
+            buf.put_u8((element.id << 4) | (element.data.len() as u8 - 1));
+        } else {
+            buf.put_u8(element.id);
+            buf.put_u8(element.data.len() as u8);
+        }
+        buf.put_slice(&element.data);
+    }
+    while !buf.len().is_multiple_of(4) {
+        buf.put_u8(0);
+    }
+    let profile = if one_byte { ONE_BYTE_PROFILE } else { TWO_BYTE_PROFILE };
+    (profile, buf.freeze())
+}
+
+/// Audio level of a packet, as sent by the client (RFC 6464)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AudioLevel {
+    /// The sender's voice activity detection flagged speech
+    pub voice: bool,
+    /// Level in -dBov: 0 is the loudest, 127 silence
+    pub level: u8,
+}
+
+impl AudioLevel {
+    pub fn new(voice: bool, level: u8) -> Self {
+        Self {
+            voice,
+            level: level.min(127),
+        }
+    }
+
+    /// Level of PCM samples, without voice activity
+    pub fn from_samples(samples: &[i16]) -> Self {
+        if samples.is_empty() {
+            return Self::new(false, 127);
+        }
+        let sum_squares: f64 = samples.iter().map(|&s| (s as f64) * (s as f64)).sum();
+        let rms = (sum_squares / samples.len() as f64).sqrt();
+        if rms < 1.0 {
+            return Self::new(false, 127);
+        }
+        let dbov = -20.0 * (rms / i16::MAX as f64).log10();
+        Self::new(false, dbov.round().clamp(0.0, 127.0) as u8)
+    }
+
+    /// Parse the extension element data
+    pub fn parse(data: &[u8]) -> Option<Self> {
+        let byte = *data.first()?;
+        Some(Self::new(byte & 0x80 != 0, byte & 0x7F))
+    }
+
+    /// Extension element data
+    pub fn to_bytes(self) -> Bytes {
+        Bytes::copy_from_slice(&[((self.voice as u8) << 7) | self.level])
+    }
+
+    /// Whether the level is at least as loud as `threshold` (-dBov)
+    pub fn is_louder_than(self, threshold: u8) -> bool {
+        self.level <= threshold
+    }
+}
+
+/// One `a=extmap` attribute: an extension's local ID and URI
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ExtMap {
+    pub id: u8,
+    pub uri: String,
+}
+
+impl ExtMap {
+    pub fn new(id: u8, uri: &str) -> Self {
+        Self {
+            id,
+            uri: uri.to_string(),
+        }
+    }
+
+    /// Parse the attribute (`extmap:1 urn:...` or its value `1 urn:...`);
+    /// a direction after the ID is ignored
+    pub fn parse(attribute: &str) -> Option<Self> {
+        let value = attribute.strip_prefix("extmap:").unwrap_or(attribute);
+        let mut parts = value.split_whitespace();
+        let id = parts.next()?.split('/').next()?.parse().ok()?;
+        let uri = parts.next()?;
+        Some(Self::new(id, uri))
+    }
+
+    /// The attribute without `a=`
+    pub fn to_attribute(&self) -> String {
+        format!("extmap:{} {}", self.id, self.uri)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_element_forms_round_trip() {
+        let short = vec![
+            HeaderExtension::new(1, AudioLevel::new(true, 30).to_bytes()),
+            HeaderExtension::new(2, Bytes::from_static(&[0x12, 0x34, 0x56])),
+        ];
+        let (profile, data) = serialize_extensions(&short);
+        assert_eq!(profile, ONE_BYTE_PROFILE);
+        assert_eq!(&data[..], &[0x10, 0x9e, 0x22, 0x12, 0x34, 0x56, 0, 0]);
+        assert_eq!(parse_extensions(profile, &data), Some(short));
+
+        // An ID above 14 or an empty element needs the two-byte form
+        let long = vec![
+            HeaderExtension::new(1, AudioLevel::new(false, 127).to_bytes()),
+            HeaderExtension::new(20, Bytes::new()),
+        ];
+        let (profile, data) = serialize_extensions(&long);
+        assert_eq!(profile, TWO_BYTE_PROFILE);
+        assert_eq!(&data[..], &[1, 1, 0x7f, 20, 0, 0, 0, 0]);
+        assert_eq!(parse_extensions(profile, &data), Some(long));
+
+        assert_eq!(parse_extensions(0xABCD, &data), None);
+        assert_eq!(parse_extensions(ONE_BYTE_PROFILE, &[0x13, 0x01]), None);
+    }
+
+    #[test]
+    fn test_audio_level() {
+        assert_eq!(AudioLevel::parse(&[0x9e]), Some(AudioLevel::new(true, 30)));
+        assert_eq!(AudioLevel::parse(&[0x7f]), Some(AudioLevel::new(false, 127)));
+        assert_eq!(AudioLevel::from_samples(&[0; 160]).level, 127);
+        assert_eq!(AudioLevel::from_samples(&[i16::MAX; 160]).level, 0);
+        // A full-scale sine is about -3 dBov
+        let sine: Vec<i16> = (0..160)
+            .map(|i| ((i as f64 * std::f64::consts::PI / 8.0).sin() * i16::MAX as f64) as i16)
+            .collect();
+        assert_eq!(AudioLevel::from_samples(&sine).level, 3);
+        assert!(AudioLevel::new(true, 30).is_louder_than(50));
+        assert!(!AudioLevel::new(false, 80).is_louder_than(50));
+    }
+
+    #[test]
+    fn test_extmap_attribute() {
+        let extmap = ExtMap::parse("extmap:1 urn:ietf:params:rtp-hdrext:ssrc-audio-level").unwrap();
+        assert_eq!(extmap, ExtMap::new(1, AUDIO_LEVEL_URI));
+        assert_eq!(extmap.to_attribute(), "extmap:1 urn:ietf:params:rtp-hdrext:ssrc-audio-level");
+        assert_eq!(ExtMap::parse("3/sendonly urn:x"), Some(ExtMap::new(3, "urn:x")));
+        assert_eq!(ExtMap::parse("extmap-allow-mixed"), None);
+    }
+}
@@ -2,12 +2,17 @@
 //!
 //! This module implements RTP according to RFC 3550.
 
+pub mod header_extension;
 pub mod jitter_buffer;
 pub mod packet;
 pub mod rtcp;
 pub mod session;
 
+pub use header_extension::{AudioLevel, ExtMap, HeaderExtension, AUDIO_LEVEL_URI};
 pub use jitter_buffer::{JitterBuffer, JitterBufferConfig, JitterBufferStats};
 pub use packet::{RtpError, RtpPacket};
-pub use rtcp::{Goodbye, ReceiverReport, RtcpError, RtcpPacket, SenderReport, SourceDescription};
-pub use session::{RtpSession, RtpStats, SsrcGenerator};
+pub use rtcp::{
+    Goodbye, ReceiverReport, ReceptionReport, Remb, RtcpError, RtcpPacket, SenderReport,
+    SourceDescription,
+};
+pub use session::{MuxedPacket, RtpSession, RtpStats, SsrcGenerator};
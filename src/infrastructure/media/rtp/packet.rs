@@ -1,5 +1,6 @@
 //! RTP Packet Implementation (RFC 3550)
 
+use super::header_extension::{parse_extensions, serialize_extensions, AudioLevel, HeaderExtension};
 use bytes::{Buf, BufMut, Bytes, BytesMut};
 use std::fmt;
 
@@ -255,6 +256,49 @@ impl RtpPacket {
         self.extension_data = Some(data);
     }
 
+    /// Elements of the extension block (RFC 8285); empty without an
+    /// extension or with one in another form
+    pub fn header_extensions(&self) -> Vec<HeaderExtension> {
+        match (self.extension_profile, &self.extension_data) {
+            (Some(profile), Some(data)) => parse_extensions(profile, data).unwrap_or_default(),
+            _ => Vec::new(),
+        }
+    }
+
+    /// Data of the extension element with local ID `id`
+    pub fn header_extension(&self, id: u8) -> Option<Bytes> {
+        self.header_extensions()
+            .into_iter()
+            .find(|e| e.id == id)
+            .map(|e| e.data)
+    }
+
+    /// Replace the extension block with `elements`, in the one-byte form
+    /// when they all fit it; no elements removes the extension
+    pub fn set_header_extensions(&mut self, elements: &[HeaderExtension]) {
+        if elements.is_empty() {
+            self.extension = false;
+            self.extension_profile = None;
+            self.extension_data = None;
+            return;
+        }
+        let (profile, data) = serialize_extensions(elements);
+        self.set_extension(profile, data);
+    }
+
+    /// Client-to-mixer audio level (RFC 6464), negotiated with local ID `id`
+    pub fn audio_level(&self, id: u8) -> Option<AudioLevel> {
+        AudioLevel::parse(&self.header_extension(id)?)
+    }
+
+    /// Set the audio level element, keeping the other elements
+    pub fn set_audio_level(&mut self, id: u8, level: AudioLevel) {
+        let mut elements = self.header_extensions();
+        elements.retain(|e| e.id != id);
+        elements.push(HeaderExtension::new(id, level.to_bytes()));
+        self.set_header_extensions(&elements);
+    }
+
     /// Add padding
     pub fn add_padding(&mut self, target_size: usize) {
         let current_size = self.calculate_size();
@@ -340,6 +384,80 @@ mod tests {
         assert_eq!(parsed.csrc[1], 0x22222222);
     }
 
+    /// Opus packet as Chrome sends it: one-byte extensions with the audio
+    /// level (ID 1, voice, -30 dBov), abs-send-time (ID 2) and
+    /// transport-wide-cc (ID 3)
+    const CHROME_SPEECH: [u8; 31] = [
+        0x90, 0x6f, 0x1a, 0x2b, 0x00, 0x01, 0xe2, 0x40, 0x5c, 0x7a, 0x31, 0x09,
+        0xbe, 0xde, 0x00, 0x03,
+        0x10, 0x9e, 0x22, 0x4b, 0x8f, 0x3c, 0x31, 0x00, 0x2a, 0x00, 0x00, 0x00,
+        0x78, 0x0b, 0xe4,
+    ];
+
+    /// Chrome's comfort noise while muted: silence without voice
+    const CHROME_SILENCE: [u8; 27] = [
+        0x90, 0x6f, 0x1a, 0x2c, 0x00, 0x01, 0xe5, 0x60, 0x5c, 0x7a, 0x31, 0x09,
+        0xbe, 0xde, 0x00, 0x02,
+        0x10, 0x7f, 0x22, 0x4b, 0x90, 0x11, 0x00, 0x00,
+        0xf8, 0xff, 0xfe,
+    ];
+
+    #[test]
+    fn test_parse_chrome_audio_levels() {
+        let speech = RtpPacket::parse(&CHROME_SPEECH).unwrap();
+        assert_eq!(speech.payload_type, 111);
+        assert_eq!(speech.extension_profile, Some(0xBEDE));
+        assert_eq!(speech.audio_level(1), Some(AudioLevel::new(true, 30)));
+        assert_eq!(
+            speech.header_extension(2).as_deref(),
+            Some(&[0x4b, 0x8f, 0x3c][..])
+        );
+        assert_eq!(speech.header_extension(3).as_deref(), Some(&[0x00, 0x2a][..]));
+        assert_eq!(&speech.payload[..], &[0x78, 0x0b, 0xe4]);
+
+        let silence = RtpPacket::parse(&CHROME_SILENCE).unwrap();
+        assert_eq!(silence.audio_level(1), Some(AudioLevel::new(false, 127)));
+        assert_eq!(silence.audio_level(4), None);
+
+        // Relayed packets keep their extensions byte for byte
+        assert_eq!(&speech.serialize()[..], &CHROME_SPEECH[..]);
+        assert_eq!(&silence.serialize()[..], &CHROME_SILENCE[..]);
+    }
+
+    #[test]
+    fn test_header_extension_round_trip_both_forms() {
+        let mut packet = RtpPacket::new(0, 7, 160, 0x01020304, Bytes::from_static(&[0xff; 160]));
+        packet.set_audio_level(1, AudioLevel::new(true, 42));
+        let parsed = RtpPacket::parse(&packet.serialize()).unwrap();
+        assert_eq!(parsed.extension_profile, Some(0xBEDE));
+        assert_eq!(parsed.audio_level(1), Some(AudioLevel::new(true, 42)));
+        assert_eq!(parsed.payload.len(), 160);
+
+        // An ID the one-byte form cannot carry switches the block to two bytes
+        packet.set_header_extensions(&[
+            HeaderExtension::new(1, AudioLevel::new(false, 90).to_bytes()),
+            HeaderExtension::new(17, Bytes::from_static(b"mid0")),
+        ]);
+        let parsed = RtpPacket::parse(&packet.serialize()).unwrap();
+        assert_eq!(parsed.extension_profile, Some(0x1000));
+        assert_eq!(parsed.audio_level(1), Some(AudioLevel::new(false, 90)));
+        assert_eq!(parsed.header_extension(17).as_deref(), Some(&b"mid0"[..]));
+
+        packet.set_header_extensions(&[]);
+        assert!(!RtpPacket::parse(&packet.serialize()).unwrap().extension);
+    }
+
+    #[test]
+    fn test_unknown_extension_passes_through() {
+        let mut packet = RtpPacket::new(8, 1, 160, 0xAABBCCDD, Bytes::from_static(b"pcma"));
+        packet.set_extension(0x0ABC, Bytes::from_static(&[1, 2, 3, 4, 5, 6, 7, 8]));
+        let data = packet.serialize();
+        let parsed = RtpPacket::parse(&data).unwrap();
+        assert!(parsed.header_extensions().is_empty());
+        assert_eq!(parsed.audio_level(1), None);
+        assert_eq!(parsed.serialize(), data);
+    }
+
     #[test]
     fn test_rtp_min_size() {
         let data = vec![0u8; 11]; // Too short
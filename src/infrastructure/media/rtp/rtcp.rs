@@ -17,6 +17,8 @@ pub enum RtcpPacketType {
     BYE = 203,
     /// Application Defined
     APP = 204,
+    /// Payload-Specific Feedback (RFC 4585)
+    PSFB = 206,
 }
 
 impl RtcpPacketType {
@@ -27,6 +29,7 @@ impl RtcpPacketType {
             202 => Some(Self::SDES),
             203 => Some(Self::BYE),
             204 => Some(Self::APP),
+            206 => Some(Self::PSFB),
             _ => None,
         }
     }
@@ -39,6 +42,7 @@ pub enum RtcpPacket {
     ReceiverReport(ReceiverReport),
     SourceDescription(SourceDescription),
     Goodbye(Goodbye),
+    Remb(Remb),
 }
 
 impl RtcpPacket {
@@ -63,10 +67,37 @@ impl RtcpPacket {
             Some(RtcpPacketType::RR) => Ok(RtcpPacket::ReceiverReport(ReceiverReport::parse(data)?)),
             Some(RtcpPacketType::SDES) => Ok(RtcpPacket::SourceDescription(SourceDescription::parse(data)?)),
             Some(RtcpPacketType::BYE) => Ok(RtcpPacket::Goodbye(Goodbye::parse(data)?)),
+            Some(RtcpPacketType::PSFB) if byte0 & 0x1F == Remb::FMT => {
+                Ok(RtcpPacket::Remb(Remb::parse(data)?))
+            }
             _ => Err(RtcpError::UnsupportedPacketType(packet_type)),
         }
     }
 
+    /// Parse the packets of a compound RTCP packet, skipping the ones of
+    /// unsupported types
+    pub fn parse_compound(data: &[u8]) -> Result<Vec<Self>, RtcpError> {
+        let mut packets = Vec::new();
+        let mut rest = data;
+        while rest.len() >= 4 {
+            let len = (u16::from_be_bytes([rest[2], rest[3]]) as usize + 1) * 4;
+            if rest.len() < len {
+                return Err(RtcpError::PacketTooShort);
+            }
+            match Self::parse(&rest[..len]) {
+                Ok(packet) => packets.push(packet),
+                Err(RtcpError::UnsupportedPacketType(_)) => {}
+                Err(e) => return Err(e),
+            }
+            rest = &rest[len..];
+        }
+        if packets.is_empty() {
+            // Report why a lone packet was not understood
+            return Self::parse(data).map(|packet| vec![packet]);
+        }
+        Ok(packets)
+    }
+
     /// Serialize RTCP packet to bytes
     pub fn serialize(&self) -> Bytes {
         match self {
@@ -74,6 +105,7 @@ impl RtcpPacket {
             RtcpPacket::ReceiverReport(rr) => rr.serialize(),
             RtcpPacket::SourceDescription(sdes) => sdes.serialize(),
             RtcpPacket::Goodbye(bye) => bye.serialize(),
+            RtcpPacket::Remb(remb) => remb.serialize(),
         }
     }
 }
@@ -410,6 +442,102 @@ impl Goodbye {
     }
 }
 
+/// Receiver Estimated Maximum Bitrate (draft-alvestrand-rmcat-remb)
+///
+/// Asks the sender of `ssrcs` to keep its total send rate below `bitrate`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Remb {
+    pub sender_ssrc: u32,
+    /// Bits per second
+    pub bitrate: u64,
+    pub ssrcs: Vec<u32>,
+}
+
+impl Remb {
+    /// Feedback message type of application layer feedback
+    pub const FMT: u8 = 15;
+    const IDENTIFIER: &'static [u8; 4] = b"REMB";
+
+    pub fn new(sender_ssrc: u32, bitrate: u64, ssrcs: Vec<u32>) -> Self {
+        Self {
+            sender_ssrc,
+            bitrate,
+            ssrcs,
+        }
+    }
+
+    pub fn parse(data: &[u8]) -> Result<Self, RtcpError> {
+        if data.len() < 20 {
+            return Err(RtcpError::PacketTooShort);
+        }
+
+        let mut buf = data;
+        let _byte0 = buf.get_u8();
+        let _pt = buf.get_u8();
+        let length = buf.get_u16() as usize;
+
+        if data.len() < (length + 1) * 4 {
+            return Err(RtcpError::PacketTooShort);
+        }
+
+        let sender_ssrc = buf.get_u32();
+        let _media_ssrc = buf.get_u32();
+        if &buf[..4] != Self::IDENTIFIER {
+            return Err(RtcpError::UnsupportedPacketType(RtcpPacketType::PSFB as u8));
+        }
+        buf.advance(4);
+        let count = buf.get_u8() as usize;
+        let exp_mantissa = ((buf.get_u8() as u32) << 16) | buf.get_u16() as u32;
+        let exponent = exp_mantissa >> 18;
+        let mantissa = (exp_mantissa & 0x3FFFF) as u64;
+
+        let mut ssrcs = Vec::with_capacity(count);
+        for _ in 0..count {
+            if buf.remaining() < 4 {
+                break;
+            }
+            ssrcs.push(buf.get_u32());
+        }
+
+        Ok(Self {
+            sender_ssrc,
+            bitrate: mantissa << exponent,
+            ssrcs,
+        })
+    }
+
+    pub fn serialize(&self) -> Bytes {
+        let length = 4 + self.ssrcs.len();
+        let mut buf = BytesMut::with_capacity((length + 1) * 4);
+
+        buf.put_u8(0x80 | Self::FMT);
+        buf.put_u8(RtcpPacketType::PSFB as u8);
+        buf.put_u16(length as u16);
+
+        buf.put_u32(self.sender_ssrc);
+        // Media source SSRC is unused
+        buf.put_u32(0);
+        buf.put_slice(Self::IDENTIFIER);
+
+        // 6 bit exponent, 18 bit mantissa
+        let mut exponent = 0u32;
+        let mut mantissa = self.bitrate;
+        while mantissa > 0x3FFFF {
+            mantissa >>= 1;
+            exponent += 1;
+        }
+        buf.put_u8(self.ssrcs.len() as u8);
+        buf.put_u8(((exponent << 2) as u8) | ((mantissa >> 16) as u8 & 0x03));
+        buf.put_u16((mantissa & 0xFFFF) as u16);
+
+        for ssrc in &self.ssrcs {
+            buf.put_u32(*ssrc);
+        }
+
+        buf.freeze()
+    }
+}
+
 /// RTCP Errors
 #[derive(Debug, Clone, thiserror::Error)]
 pub enum RtcpError {
@@ -447,6 +575,27 @@ mod tests {
         assert_eq!(parsed.reports.len(), 0);
     }
 
+    #[test]
+    fn test_remb() {
+        let remb = Remb::new(0x01020304, 1_500_000, vec![0xAABBCCDD]);
+        let mut compound = ReceiverReport::new(0x01020304).serialize().to_vec();
+        compound.extend_from_slice(&remb.serialize());
+
+        let packets = RtcpPacket::parse_compound(&compound).unwrap();
+        assert_eq!(packets.len(), 2);
+        match &packets[1] {
+            // Above 2^18, so sent with an exponent
+            RtcpPacket::Remb(parsed) => assert_eq!(parsed, &remb),
+            other => panic!("expected REMB, got {:?}", other),
+        }
+
+        let small = Remb::new(1, 32_000, vec![]);
+        match RtcpPacket::parse(&small.serialize()).unwrap() {
+            RtcpPacket::Remb(parsed) => assert_eq!(parsed, small),
+            other => panic!("expected REMB, got {:?}", other),
+        }
+    }
+
     #[test]
     fn test_goodbye() {
         let bye = Goodbye::new(0x11223344);
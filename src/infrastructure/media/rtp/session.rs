@@ -3,13 +3,15 @@
 use super::packet::{RtpError, RtpPacket};
 use bytes::Bytes;
 use rand::Rng;
-use std::sync::atomic::{AtomicU16, AtomicU32, Ordering};
-use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, AtomicU16, AtomicU32, Ordering};
+use std::sync::{Arc, Mutex};
 use tracing::{debug, warn};
 
 /// RTP Session
 ///
-/// Manages RTP packet generation and reception for a single media stream
+/// Manages RTP packet generation and reception for a single media stream.
+/// A session owns no sockets or tasks; `close()` only freezes its counters
+/// into the final statistics.
 pub struct RtpSession {
     /// Synchronization source identifier (randomly generated)
     ssrc: u32,
@@ -25,6 +27,10 @@ pub struct RtpSession {
     payload_type: u8,
     /// Clock rate (samples per second)
     clock_rate: u32,
+    /// Set once by `close()`
+    closed: AtomicBool,
+    /// Counters at close time
+    final_stats: Mutex<Option<RtpStats>>,
 }
 
 impl RtpSession {
@@ -40,6 +46,8 @@ impl RtpSession {
             bytes_sent: Arc::new(AtomicU32::new(0)),
             payload_type,
             clock_rate,
+            closed: AtomicBool::new(false),
+            final_stats: Mutex::new(None),
         }
     }
 
@@ -55,6 +63,42 @@ impl RtpSession {
             bytes_sent: Arc::new(AtomicU32::new(0)),
             payload_type,
             clock_rate,
+            closed: AtomicBool::new(false),
+            final_stats: Mutex::new(None),
+        }
+    }
+
+    /// Session replacing one sent with `previous_ssrc` before a restart
+    ///
+    /// The SSRC is new and the sequence number and timestamp start at
+    /// random offsets, so receivers do not take the new packets for
+    /// duplicates or replays of the old ones.
+    pub fn recovered(previous_ssrc: u32, payload_type: u8, clock_rate: u32) -> Self {
+        let mut rng = rand::thread_rng();
+        let ssrc = loop {
+            let ssrc: u32 = rng.gen();
+            if ssrc != previous_ssrc && ssrc != 0 {
+                break ssrc;
+            }
+        };
+        Self::with_ssrc(ssrc, payload_type, clock_rate)
+    }
+
+    /// Session sending another payload type in the same stream
+    ///
+    /// The SSRC, sequence numbers, timestamp base and counters carry on,
+    /// shared with this session, as after a mid-call codec change.
+    pub fn continuing(&self, payload_type: u8, clock_rate: u32) -> Self {
+        Self {
+            ssrc: self.ssrc,
+            sequence: self.sequence.clone(),
+            timestamp_base: self.timestamp_base,
+            packets_sent: self.packets_sent.clone(),
+            bytes_sent: self.bytes_sent.clone(),
+            payload_type,
+            clock_rate,
+            closed: AtomicBool::new(false),
+            final_stats: Mutex::new(None),
         }
     }
 
@@ -63,6 +107,16 @@ impl RtpSession {
         self.ssrc
     }
 
+    /// Get payload type
+    pub fn payload_type(&self) -> u8 {
+        self.payload_type
+    }
+
+    /// Get clock rate
+    pub fn clock_rate(&self) -> u32 {
+        self.clock_rate
+    }
+
     /// Get current sequence number (without incrementing)
     pub fn sequence(&self) -> u16 {
         self.sequence.load(Ordering::Relaxed)
@@ -125,6 +179,36 @@ impl RtpSession {
         self.bytes_sent.load(Ordering::Relaxed)
     }
 
+    /// Send statistics; the final snapshot once closed
+    pub fn stats(&self) -> RtpStats {
+        if let Some(stats) = self.final_stats.lock().unwrap().as_ref() {
+            return stats.clone();
+        }
+        RtpStats {
+            packets_sent: self.packets_sent(),
+            bytes_sent: self.bytes_sent(),
+            ..RtpStats::default()
+        }
+    }
+
+    /// Freeze the statistics (idempotent)
+    pub async fn close(&self) {
+        if self.closed.swap(true, Ordering::SeqCst) {
+            return;
+        }
+        let stats = self.stats();
+        debug!(
+            "RTP session {:08x} closed: {} packets, {} bytes sent",
+            self.ssrc, stats.packets_sent, stats.bytes_sent
+        );
+        *self.final_stats.lock().unwrap() = Some(stats);
+    }
+
+    /// Whether `close()` was called
+    pub fn is_closed(&self) -> bool {
+        self.closed.load(Ordering::SeqCst)
+    }
+
     /// Validate received packet
     pub fn validate_packet(&self, packet: &RtpPacket) -> Result<(), RtpError> {
         if packet.payload_type != self.payload_type {
@@ -137,6 +221,29 @@ impl RtpSession {
 
         Ok(())
     }
+
+    /// Tell RTP from RTCP on a port carrying both (RFC 5761 section 4)
+    ///
+    /// RTCP packet types 192-223 are the RTP payload types 64-95 with the
+    /// marker bit set, which RTP sessions therefore never use. `None` for a
+    /// packet that is neither (too short or not version 2).
+    pub fn demux(data: &[u8]) -> Option<MuxedPacket> {
+        if data.len() < 4 || data[0] >> 6 != 2 {
+            return None;
+        }
+        match data[1] {
+            192..=223 => Some(MuxedPacket::Rtcp),
+            _ if data.len() >= 12 => Some(MuxedPacket::Rtp),
+            _ => None,
+        }
+    }
+}
+
+/// Kind of a packet received on a port multiplexing RTP and RTCP
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MuxedPacket {
+    Rtp,
+    Rtcp,
 }
 
 /// SSRC Generator
@@ -276,4 +383,28 @@ mod tests {
         generator.release(ssrc).await;
         assert!(!generator.is_used(ssrc).await);
     }
+
+    #[test]
+    fn test_demux_by_packet_type() {
+        use super::super::rtcp::{ReceiverReport, SenderReport};
+
+        let session = RtpSession::new(0, 8000);
+        let mut rtp = session
+            .create_packet(Bytes::from_static(b"test"), 0, true)
+            .serialize()
+            .to_vec();
+        assert_eq!(RtpSession::demux(&rtp), Some(MuxedPacket::Rtp));
+
+        let sr = SenderReport::new(session.ssrc(), 0, 1, 4);
+        assert_eq!(RtpSession::demux(&sr.serialize()), Some(MuxedPacket::Rtcp));
+        let rr = ReceiverReport::new(session.ssrc());
+        assert_eq!(RtpSession::demux(&rr.serialize()), Some(MuxedPacket::Rtcp));
+
+        // Dynamic payload type 96 with the marker bit is still RTP
+        rtp[1] = 0x80 | 96;
+        assert_eq!(RtpSession::demux(&rtp), Some(MuxedPacket::Rtp));
+        rtp[0] = 0x40;
+        assert_eq!(RtpSession::demux(&rtp), None);
+        assert_eq!(RtpSession::demux(&[0x80]), None);
+    }
 }
@@ -33,6 +33,7 @@ impl std::fmt::Display for SrtpError {
 impl std::error::Error for SrtpError {}
 
 /// Replay protection using sliding window
+#[derive(Clone)]
 struct ReplayWindow {
     /// Highest sequence number seen
     highest_seq: u64,
@@ -72,28 +73,30 @@ impl ReplayWindow {
     /// Update window after accepting packet
     fn update(&mut self, seq: u64) {
         if seq > self.highest_seq {
-            // Shift window
-            let shift = (seq - self.highest_seq).min(64);
-            self.window <<= shift;
+            // Shift window; a jump of 64 or more clears it
+            let shift = u32::try_from(seq - self.highest_seq).unwrap_or(u32::MAX);
+            self.window = self.window.checked_shl(shift).unwrap_or(0);
             self.window |= 1; // Mark current packet
             self.highest_seq = seq;
         } else {
             // Mark bit in window
             let diff = self.highest_seq - seq;
-            let bit = 1u64 << diff;
-            self.window |= bit;
+            if let Some(bit) = 1u64.checked_shl(diff as u32).filter(|_| diff < 64) {
+                self.window |= bit;
+            }
         }
     }
 }
 
 /// SRTP stream context for a single SSRC
+#[derive(Clone)]
 struct SrtpStreamContext {
     /// Replay protection window
     replay_window: ReplayWindow,
     /// ROC (Rollover Counter) for 32-bit sequence number extension
     roc: u32,
-    /// Last sequence number seen
-    last_seq: u16,
+    /// Highest sequence number seen, none before the first packet
+    last_seq: Option<u16>,
 }
 
 impl SrtpStreamContext {
@@ -101,35 +104,66 @@ impl SrtpStreamContext {
         Self {
             replay_window: ReplayWindow::new(),
             roc: 0,
-            last_seq: 0,
+            last_seq: None,
         }
     }
 
-    /// Get packet index from sequence number
+    /// Estimate the packet index of a sequence number (RFC 3711 Appendix A)
     /// packet_index = ROC * 65536 + SEQ
-    fn get_packet_index(&mut self, seq: u16) -> u64 {
-        // Estimate ROC
-        let mut roc = self.roc;
-
-        if self.last_seq < 32768 {
-            if seq - self.last_seq > 32768 {
-                // Wrapped backwards
-                roc = roc.saturating_sub(1);
+    fn estimate_index(&self, seq: u16) -> u64 {
+        let Some(last_seq) = self.last_seq else {
+            return seq as u64;
+        };
+
+        let roc = self.roc as i64;
+        let (s_l, seq) = (last_seq as i64, seq as i64);
+        let v = if s_l < 32768 {
+            if seq - s_l > 32768 {
+                // Late packet from before the last wrap
+                roc - 1
+            } else {
+                roc
             }
+        } else if s_l - 32768 > seq {
+            // Sequence number wrapped
+            roc + 1
         } else {
-            if self.last_seq - seq > 32768 {
-                // Wrapped forwards
-                roc = roc.saturating_add(1);
+            roc
+        };
+
+        ((v.max(0) as u64) << 16) | seq as u64
+    }
+
+    /// Advance ROC and highest sequence number past an accepted packet
+    fn update(&mut self, packet_index: u64) {
+        let roc = (packet_index >> 16) as u32;
+        let seq = packet_index as u16;
+        match self.last_seq {
+            Some(last_seq) if roc < self.roc || (roc == self.roc && seq <= last_seq) => {}
+            _ => {
+                self.roc = roc;
+                self.last_seq = Some(seq);
             }
         }
+    }
 
-        self.last_seq = seq;
-        self.roc = roc;
-
-        (roc as u64) * 65536 + (seq as u64)
+    /// Get packet index from sequence number and advance the stream
+    fn get_packet_index(&mut self, seq: u16) -> u64 {
+        let packet_index = self.estimate_index(seq);
+        self.update(packet_index);
+        packet_index
     }
 }
 
+/// Authenticated portion of an SRTP packet with the ROC appended (RFC 3711
+/// section 4.2)
+fn with_roc(packet: &[u8], packet_index: u64) -> Vec<u8> {
+    let mut authenticated = Vec::with_capacity(packet.len() + 4);
+    authenticated.extend_from_slice(packet);
+    authenticated.extend_from_slice(&((packet_index >> 16) as u32).to_be_bytes());
+    authenticated
+}
+
 /// SRTP context for encrypting/decrypting RTP packets
 pub struct SrtpContext {
     /// Protection profile
@@ -170,6 +204,18 @@ impl SrtpContext {
         self.replay_protection = false;
     }
 
+    /// Continue the rollover counters and replay windows of another
+    /// context, so packet indexes carry on across a key change
+    pub fn inherit_streams(&self, from: &SrtpContext) {
+        let streams = from.streams.lock().unwrap().clone();
+        *self.streams.lock().unwrap() = streams;
+    }
+
+    /// Rollover counter of a stream, none before its first packet
+    pub fn roc(&self, ssrc: u32) -> Option<u32> {
+        self.streams.lock().unwrap().get(&ssrc).map(|stream| stream.roc)
+    }
+
     /// Parse RTP packet header to extract SSRC and sequence number
     fn parse_rtp_header(packet: &[u8]) -> Result<(u32, u16), SrtpError> {
         if packet.len() < 12 {
@@ -234,10 +280,10 @@ impl SrtpContext {
             xor_keystream(&mut packet[header_len..], &keystream);
         }
 
-        // Compute authentication tag
+        // Compute authentication tag over the packet and ROC
         let auth_tag = compute_auth_tag(
             &self.keys.srtp_auth_key,
-            packet,
+            &with_roc(packet, packet_index),
             self.profile.auth_tag_len(),
         );
 
@@ -248,6 +294,9 @@ impl SrtpContext {
     }
 
     /// Decrypt RTP packet in-place
+    ///
+    /// The packet is left untouched when it fails authentication or the
+    /// replay check, and the stream only advances past authenticated packets.
     pub fn decrypt_rtp(&self, packet: &mut Vec<u8>) -> Result<(), SrtpError> {
         let tag_len = self.profile.auth_tag_len();
 
@@ -255,31 +304,38 @@ impl SrtpContext {
             return Err(SrtpError::InvalidPacket("Packet too short".to_string()));
         }
 
-        // Split off authentication tag
+        // Parse header
         let packet_len = packet.len() - tag_len;
-        let auth_tag = packet[packet_len..].to_vec();
-        packet.truncate(packet_len);
+        let (ssrc, seq) = Self::parse_rtp_header(packet)?;
+        let header_len = Self::get_rtp_header_len(&packet[..packet_len])?;
+
+        // Estimate packet index
+        let mut streams = self.streams.lock().unwrap();
+        let mut stream = streams.get(&ssrc).cloned().unwrap_or_else(SrtpStreamContext::new);
+        let packet_index = stream.estimate_index(seq);
 
         // Verify authentication tag
-        if !verify_auth_tag(&self.keys.srtp_auth_key, packet, &auth_tag) {
+        if !verify_auth_tag(
+            &self.keys.srtp_auth_key,
+            &with_roc(&packet[..packet_len], packet_index),
+            &packet[packet_len..],
+        ) {
             return Err(SrtpError::AuthenticationFailed);
         }
 
-        // Parse header
-        let (ssrc, seq) = Self::parse_rtp_header(packet)?;
-        let header_len = Self::get_rtp_header_len(packet)?;
-
-        // Get packet index and check replay
-        let mut streams = self.streams.lock().unwrap();
-        let stream = streams.entry(ssrc).or_insert_with(SrtpStreamContext::new);
-        let packet_index = stream.get_packet_index(seq);
-
+        // Check replay
         if self.replay_protection {
             if !stream.replay_window.check(packet_index) {
                 return Err(SrtpError::ReplayAttack);
             }
             stream.replay_window.update(packet_index);
         }
+        stream.update(packet_index);
+        streams.insert(ssrc, stream);
+        drop(streams);
+
+        // Split off authentication tag
+        packet.truncate(packet_len);
 
         // Generate IV
         let iv = generate_iv(&self.keys.srtp_salt, ssrc, packet_index);
@@ -341,6 +397,12 @@ mod tests {
 
         // Too old (more than 64 packets behind)
         assert!(!window.check(1));
+
+        // A jump past the window clears it
+        window.update(1000);
+        assert!(!window.check(1000));
+        assert!(window.check(999));
+        assert!(window.check(937));
     }
 
     #[test]
@@ -353,6 +415,65 @@ mod tests {
         assert_eq!(stream.get_packet_index(102), 102);
     }
 
+    #[test]
+    fn test_stream_context_rollover() {
+        let mut stream = SrtpStreamContext::new();
+
+        assert_eq!(stream.get_packet_index(65534), 65534);
+        assert_eq!(stream.get_packet_index(65535), 65535);
+        // Wrap
+        assert_eq!(stream.get_packet_index(0), 65536);
+        assert_eq!(stream.get_packet_index(1), 65537);
+        // Late packet from before the wrap keeps the ROC
+        assert_eq!(stream.get_packet_index(65533), 65533);
+        assert_eq!(stream.roc, 1);
+        assert_eq!(stream.get_packet_index(2), 65538);
+
+        // A late packet just after the start does not underflow
+        let mut stream = SrtpStreamContext::new();
+        stream.get_packet_index(10);
+        assert_eq!(stream.get_packet_index(60000), 60000);
+    }
+
+    #[test]
+    fn test_srtp_across_rollover() {
+        let master_key = SrtpMasterKey::generate(SrtpProfile::Aes128CmHmacSha1_80);
+        let sender = SrtpContext::new(master_key.clone(), SrtpProfile::Aes128CmHmacSha1_80);
+        let receiver = SrtpContext::new(master_key, SrtpProfile::Aes128CmHmacSha1_80);
+
+        let mut seq = 65500u16;
+        for _ in 0..100 {
+            let mut packet = create_test_rtp_packet(0x12345678, seq, 40);
+            let original = packet.clone();
+            sender.encrypt_rtp(&mut packet).unwrap();
+            receiver.decrypt_rtp(&mut packet).unwrap();
+            assert_eq!(packet, original);
+            seq = seq.wrapping_add(1);
+        }
+
+        assert_eq!(sender.roc(0x12345678), Some(1));
+        assert_eq!(receiver.roc(0x12345678), Some(1));
+    }
+
+    #[test]
+    fn test_forged_packet_does_not_advance_stream() {
+        let master_key = SrtpMasterKey::generate(SrtpProfile::Aes128CmHmacSha1_80);
+        let ctx = SrtpContext::new(master_key, SrtpProfile::Aes128CmHmacSha1_80);
+
+        let mut packet = create_test_rtp_packet(0x12345678, 100, 40);
+        ctx.encrypt_rtp(&mut packet).unwrap();
+        ctx.decrypt_rtp(&mut packet).unwrap();
+
+        // Unauthenticated packet far ahead
+        let mut forged = create_test_rtp_packet(0x12345678, 40000, 40);
+        forged.extend_from_slice(&[0u8; 10]);
+        let before = forged.clone();
+        assert_eq!(ctx.decrypt_rtp(&mut forged), Err(SrtpError::AuthenticationFailed));
+        assert_eq!(forged, before);
+        assert_eq!(ctx.roc(0x12345678), Some(0));
+        assert_eq!(ctx.streams.lock().unwrap()[&0x12345678].last_seq, Some(100));
+    }
+
     #[test]
     fn test_parse_rtp_header() {
         let packet = create_test_rtp_packet(0x12345678, 1000, 100);
@@ -255,18 +255,18 @@ pub fn derive_session_keys(
 
 /// Compute HMAC-SHA1 authentication tag
 pub fn compute_auth_tag(key: &[u8], data: &[u8], tag_len: usize) -> Vec<u8> {
-    let mut mac = HmacSha1::new_from_slice(key).expect("HMAC key length");
+    let mut mac = <HmacSha1 as hmac::Mac>::new_from_slice(key).expect("HMAC key length");
     mac.update(data);
     let result = mac.finalize();
     let bytes = result.into_bytes();
     bytes[..tag_len].to_vec()
 }
 
-/// Verify HMAC-SHA1 authentication tag
+/// Verify HMAC-SHA1 authentication tag, truncated to the tag's length
 pub fn verify_auth_tag(key: &[u8], data: &[u8], expected_tag: &[u8]) -> bool {
-    let mut mac = HmacSha1::new_from_slice(key).expect("HMAC key length");
+    let mut mac = <HmacSha1 as hmac::Mac>::new_from_slice(key).expect("HMAC key length");
     mac.update(data);
-    mac.verify_slice(expected_tag).is_ok()
+    mac.verify_truncated_left(expected_tag).is_ok()
 }
 
 /// Generate IV for AES-CM encryption
@@ -14,6 +14,8 @@ pub use crypto::{
 pub use context::{SrtpContext, SrtpError};
 pub use srtcp::SrtcpContext;
 
+use std::time::{Duration, Instant};
+
 /// Combined SRTP/SRTCP context for a media session
 pub struct MediaCryptoContext {
     /// SRTP context for RTP packets
@@ -22,6 +24,8 @@ pub struct MediaCryptoContext {
     pub srtcp: SrtcpContext,
     /// Protection profile
     pub profile: SrtpProfile,
+    /// Context of the replaced key and until when it still decrypts
+    previous: Option<(Box<MediaCryptoContext>, Instant)>,
 }
 
 impl MediaCryptoContext {
@@ -52,6 +56,35 @@ impl MediaCryptoContext {
             srtp,
             srtcp,
             profile,
+            previous: None,
+        }
+    }
+
+    /// Context for a new master key
+    ///
+    /// Packet indexes carry on from this context, and packets protected
+    /// with the old key still decrypt for `overlap` so those in flight
+    /// during the switch are not lost.
+    pub fn rekey(self, master_key: SrtpMasterKey, profile: SrtpProfile, overlap: Duration) -> Self {
+        let mut next = Self::new(master_key, profile);
+        next.srtp.inherit_streams(&self.srtp);
+        let old = Self {
+            previous: None,
+            ..self
+        };
+        next.previous = Some((Box::new(old), Instant::now() + overlap));
+        next
+    }
+
+    /// Whether packets protected with the replaced key are still accepted
+    pub fn in_overlap(&self) -> bool {
+        self.previous_context().is_some()
+    }
+
+    fn previous_context(&self) -> Option<&MediaCryptoContext> {
+        match &self.previous {
+            Some((previous, until)) if Instant::now() < *until => Some(previous),
+            _ => None,
         }
     }
 
@@ -60,9 +93,15 @@ impl MediaCryptoContext {
         self.srtp.encrypt_rtp(packet)
     }
 
-    /// Decrypt RTP packet
+    /// Decrypt RTP packet, with the replaced key during the overlap
     pub fn unprotect_rtp(&self, packet: &mut Vec<u8>) -> Result<(), SrtpError> {
-        self.srtp.decrypt_rtp(packet)
+        match self.srtp.decrypt_rtp(packet) {
+            Err(SrtpError::AuthenticationFailed) => match self.previous_context() {
+                Some(previous) => previous.srtp.decrypt_rtp(packet),
+                None => Err(SrtpError::AuthenticationFailed),
+            },
+            result => result,
+        }
     }
 
     /// Encrypt RTCP packet
@@ -70,9 +109,15 @@ impl MediaCryptoContext {
         self.srtcp.encrypt_rtcp(packet)
     }
 
-    /// Decrypt RTCP packet
+    /// Decrypt RTCP packet, with the replaced key during the overlap
     pub fn unprotect_rtcp(&self, packet: &mut Vec<u8>) -> Result<(), SrtpError> {
-        self.srtcp.decrypt_rtcp(packet)
+        match self.srtcp.decrypt_rtcp(packet) {
+            Err(SrtpError::AuthenticationFailed) => match self.previous_context() {
+                Some(previous) => previous.srtcp.decrypt_rtcp(packet),
+                None => Err(SrtpError::AuthenticationFailed),
+            },
+            result => result,
+        }
     }
 }
 
@@ -165,4 +210,72 @@ mod tests {
             assert_eq!(rtcp, rtcp_orig);
         }
     }
+
+    #[test]
+    fn test_rekey_after_rollover_loses_no_packets() {
+        let profile = SrtpProfile::Aes128CmHmacSha1_80;
+        let old_key = SrtpMasterKey::generate(profile);
+        let new_key = SrtpMasterKey::generate(profile);
+        let mut sender = MediaCryptoContext::new(old_key.clone(), profile);
+        let mut receiver = MediaCryptoContext::new(old_key, profile);
+
+        let mut decrypted = 0;
+        let mut seq = 65500u16;
+        let send = |ctx: &MediaCryptoContext, seq: u16| {
+            let mut packet = create_test_rtp_packet(0x12345678, seq);
+            ctx.protect_rtp(&mut packet).unwrap();
+            packet
+        };
+
+        // Cross the sequence number wrap on the old key
+        for _ in 0..100 {
+            let mut packet = send(&sender, seq);
+            receiver.unprotect_rtp(&mut packet).unwrap();
+            decrypted += 1;
+            seq = seq.wrapping_add(1);
+        }
+
+        // Packets still in flight when the receiver switches keys
+        let in_flight: Vec<Vec<u8>> = (0..5)
+            .map(|i| send(&sender, seq.wrapping_add(i)))
+            .collect();
+        seq = seq.wrapping_add(5);
+
+        receiver = receiver.rekey(new_key.clone(), profile, Duration::from_secs(2));
+        sender = sender.rekey(new_key, profile, Duration::from_secs(2));
+        assert!(receiver.in_overlap());
+
+        for mut packet in in_flight {
+            receiver.unprotect_rtp(&mut packet).unwrap();
+            decrypted += 1;
+        }
+        for _ in 0..50 {
+            let mut packet = send(&sender, seq);
+            let original = create_test_rtp_packet(0x12345678, seq);
+            receiver.unprotect_rtp(&mut packet).unwrap();
+            assert_eq!(packet, original);
+            decrypted += 1;
+            seq = seq.wrapping_add(1);
+        }
+
+        assert_eq!(decrypted, 155);
+        assert_eq!(receiver.srtp.roc(0x12345678), Some(1));
+    }
+
+    #[test]
+    fn test_replaced_key_rejected_after_overlap() {
+        let profile = SrtpProfile::Aes128CmHmacSha1_80;
+        let old_key = SrtpMasterKey::generate(profile);
+        let sender = MediaCryptoContext::new(old_key.clone(), profile);
+        let receiver = MediaCryptoContext::new(old_key, profile).rekey(
+            SrtpMasterKey::generate(profile),
+            profile,
+            Duration::ZERO,
+        );
+
+        let mut packet = create_test_rtp_packet(0x12345678, 1);
+        sender.protect_rtp(&mut packet).unwrap();
+        assert!(!receiver.in_overlap());
+        assert_eq!(receiver.unprotect_rtp(&mut packet), Err(SrtpError::AuthenticationFailed));
+    }
 }
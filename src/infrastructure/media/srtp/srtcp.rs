@@ -111,16 +111,15 @@ impl SrtcpContext {
             return Err(SrtpError::InvalidPacket("SRTCP packet too short".to_string()));
         }
 
-        // Split off authentication tag
+        // Verify authentication tag, leaving a failed packet untouched
         let packet_len = packet.len() - tag_len;
-        let auth_tag = packet[packet_len..].to_vec();
-        packet.truncate(packet_len);
-
-        // Verify authentication tag
-        if !verify_auth_tag(&self.srtp_auth_key, packet, &auth_tag) {
+        if !verify_auth_tag(&self.srtp_auth_key, &packet[..packet_len], &packet[packet_len..]) {
             return Err(SrtpError::AuthenticationFailed);
         }
 
+        // Split off authentication tag
+        packet.truncate(packet_len);
+
         // Extract E flag and SRTCP index (last 4 bytes before auth tag)
         let e_and_index_pos = packet.len() - 4;
         let e_and_index = u32::from_be_bytes([
@@ -1,12 +1,22 @@
 //! Media Stream Management
 
-use super::rtp::{RtpPacket, RtpSession, SenderReport};
+use super::bandwidth::{BandwidthEstimate, BandwidthEstimator};
+use super::codec::PayloadMap;
+use super::decode_health::{DecodeCounters, DecodeHealth};
+use super::latch::{LatchConfig, RtpLatch};
+use super::port_allocator::{PortLease, RtpPortAllocator};
+use super::rtp::{
+    MuxedPacket, ReceiverReport, ReceptionReport, RtcpPacket, RtpPacket, RtpSession, RtpStats,
+    SenderReport,
+};
 use super::srtp::{MediaCryptoContext, SrtpMasterKey, SrtpProfile};
 use bytes::Bytes;
-use std::net::SocketAddr;
-use std::sync::Arc;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+use std::sync::{Arc, Mutex};
 use tokio::net::UdpSocket;
-use tokio::sync::RwLock;
+use tokio::sync::{broadcast, RwLock};
+use tokio::task::JoinHandle;
 use tokio::time::{interval, Duration};
 use tracing::{debug, error, info, warn};
 
@@ -19,66 +29,446 @@ pub enum StreamDirection {
     Inactive,
 }
 
+impl StreamDirection {
+    /// SDP direction attribute name
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            StreamDirection::SendOnly => "sendonly",
+            StreamDirection::RecvOnly => "recvonly",
+            StreamDirection::SendRecv => "sendrecv",
+            StreamDirection::Inactive => "inactive",
+        }
+    }
+
+    /// Parse an SDP direction attribute name
+    pub fn from_attr(attr: &str) -> Option<Self> {
+        match attr {
+            "sendonly" => Some(StreamDirection::SendOnly),
+            "recvonly" => Some(StreamDirection::RecvOnly),
+            "sendrecv" => Some(StreamDirection::SendRecv),
+            "inactive" => Some(StreamDirection::Inactive),
+            _ => None,
+        }
+    }
+
+    /// The same stream seen from the other end
+    pub fn reversed(&self) -> Self {
+        match self {
+            StreamDirection::SendOnly => StreamDirection::RecvOnly,
+            StreamDirection::RecvOnly => StreamDirection::SendOnly,
+            other => *other,
+        }
+    }
+
+    /// Whether media flows from this end
+    pub fn sends(&self) -> bool {
+        matches!(self, StreamDirection::SendOnly | StreamDirection::SendRecv)
+    }
+
+    /// Whether media is accepted at this end
+    pub fn receives(&self) -> bool {
+        matches!(self, StreamDirection::RecvOnly | StreamDirection::SendRecv)
+    }
+}
+
+/// Ports tried before giving up when allocated ports are taken by
+/// another process
+const MAX_BIND_ATTEMPTS: usize = 16;
+
+/// Received packets buffered per subscriber before it lags
+const RECEIVED_PACKETS_CAPACITY: usize = 256;
+
+/// Time between our RTCP reports
+const RTCP_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Static payload type of comfort noise (RFC 3389)
+const COMFORT_NOISE_PT: u8 = 13;
+
+/// Noise level sent in comfort noise, in -dBov
+const COMFORT_NOISE_LEVEL: u8 = 70;
+
 /// Media Stream
 ///
-/// Manages RTP and RTCP for a single media stream
+/// Manages RTP and RTCP for a single media stream. Call `close()` when the
+/// stream is no longer needed: it stops the RTP/RTCP tasks and freezes the
+/// statistics. The port pair returns to its allocator once the stream is
+/// dropped and its sockets are closed.
+///
+/// RTCP uses the port above the RTP port, or the RTP port itself once
+/// multiplexing is negotiated (RFC 5761). A stream created muxed has no
+/// RTCP port at all.
+///
+/// With latching enabled, media goes to the address the remote's media
+/// comes from rather than the one in its SDP (see [`RtpLatch`]).
+///
+/// Received Opus packets are checked for decode errors (see
+/// [`DecodeHealth`]); with comfort noise on, undecodable ones reach
+/// subscribers as comfort noise instead.
+///
+/// With bandwidth estimation on, the stream reports what it can receive in
+/// REMB packets alongside its RTCP reports (see [`BandwidthEstimator`]).
 pub struct MediaStream {
-    /// RTP session
-    rtp_session: Arc<RtpSession>,
+    /// RTP session (replaced when the payload type changes)
+    rtp_session: std::sync::RwLock<Arc<RtpSession>>,
+    /// Payload types negotiated with the remote
+    payloads: Arc<std::sync::RwLock<PayloadMap>>,
     /// Local RTP socket
-    rtp_socket: Arc<UdpSocket>,
-    /// Local RTCP socket
-    rtcp_socket: Arc<UdpSocket>,
+    rtp_socket: Arc<LocalSocket>,
+    /// Local RTCP socket, absent on a stream created muxed
+    rtcp_socket: Option<Arc<LocalSocket>>,
+    /// RTCP multiplexed on the RTP port
+    rtcp_mux: Arc<AtomicBool>,
+    /// Time between our RTCP reports
+    rtcp_interval: Duration,
     /// Remote RTP address
     remote_rtp: Arc<RwLock<Option<SocketAddr>>>,
     /// Remote RTCP address
     remote_rtcp: Arc<RwLock<Option<SocketAddr>>>,
+    /// Remote RTP address from the SDP, which latching may override
+    sdp_remote_rtp: Mutex<Option<SocketAddr>>,
+    /// Symmetric RTP latching
+    latch: Arc<Mutex<RtpLatch>>,
+    /// Decode errors of received Opus packets
+    decode_health: Arc<Mutex<DecodeHealth>>,
+    /// Undecodable packets replaced with comfort noise
+    comfort_noise: Arc<AtomicBool>,
+    /// Bandwidth estimation of a leg that negotiated goog-remb
+    bandwidth: Option<Arc<Mutex<BandwidthEstimator>>>,
     /// Stream direction
     direction: Arc<RwLock<StreamDirection>>,
     /// Running flag
     running: Arc<RwLock<bool>>,
     /// SRTP crypto context (optional)
     srtp_context: Arc<RwLock<Option<MediaCryptoContext>>>,
+    /// RTP receiver and RTCP sender tasks
+    tasks: Mutex<Vec<JoinHandle<()>>>,
+    /// Allocator the local port pair came from
+    port_allocator: Option<Arc<RtpPortAllocator>>,
+    /// Received packet counters
+    packets_received: Arc<AtomicU32>,
+    bytes_received: Arc<AtomicU32>,
+    /// Received packets, for subscribers
+    received: broadcast::Sender<RtpPacket>,
+    /// SSRC and sequence number of the last packet received, reported on
+    /// in our RTCP reports
+    last_received: Arc<Mutex<Option<(u32, u16)>>>,
+    /// Received RTCP packets, for subscribers
+    received_rtcp: broadcast::Sender<RtcpPacket>,
+    /// Statistics at close time
+    final_stats: Mutex<Option<RtpStats>>,
+    /// Set once by `close()`
+    closed: AtomicBool,
 }
 
 impl MediaStream {
-    /// Create a new media stream
+    /// Create a new media stream on IPv4
     pub async fn new(
         local_rtp_port: u16,
         payload_type: u8,
         clock_rate: u32,
+    ) -> Result<Self, std::io::Error> {
+        Self::bind(
+            IpAddr::V4(Ipv4Addr::UNSPECIFIED),
+            local_rtp_port,
+            payload_type,
+            clock_rate,
+        )
+        .await
+    }
+
+    /// Create a new media stream with sockets bound on `bind_ip`
+    ///
+    /// The address family should match the signaling leg (`::` for a call
+    /// set up over IPv6).
+    pub async fn bind(
+        bind_ip: IpAddr,
+        local_rtp_port: u16,
+        payload_type: u8,
+        clock_rate: u32,
+    ) -> Result<Self, std::io::Error> {
+        Self::bind_ports(bind_ip, local_rtp_port, payload_type, clock_rate, false, None).await
+    }
+
+    /// Create a media stream multiplexing RTCP on its only port
+    pub async fn bind_muxed(
+        bind_ip: IpAddr,
+        local_rtp_port: u16,
+        payload_type: u8,
+        clock_rate: u32,
+    ) -> Result<Self, std::io::Error> {
+        Self::bind_ports(bind_ip, local_rtp_port, payload_type, clock_rate, true, None).await
+    }
+
+    async fn bind_ports(
+        bind_ip: IpAddr,
+        local_rtp_port: u16,
+        payload_type: u8,
+        clock_rate: u32,
+        rtcp_mux: bool,
+        lease: Option<Arc<PortLease>>,
     ) -> Result<Self, std::io::Error> {
         // Bind RTP socket
-        let rtp_addr = format!("0.0.0.0:{}", local_rtp_port);
-        let rtp_socket = UdpSocket::bind(&rtp_addr).await?;
+        let rtp_addr = SocketAddr::new(bind_ip, local_rtp_port);
+        let rtp_socket = LocalSocket {
+            socket: UdpSocket::bind(rtp_addr).await?,
+            _lease: lease.clone(),
+        };
         info!("RTP socket bound to {}", rtp_addr);
 
-        // Bind RTCP socket (RTP port + 1)
-        let rtcp_addr = format!("0.0.0.0:{}", local_rtp_port + 1);
-        let rtcp_socket = UdpSocket::bind(&rtcp_addr).await?;
-        info!("RTCP socket bound to {}", rtcp_addr);
+        // Bind RTCP socket (RTP port + 1) unless RTCP shares the RTP port
+        let rtcp_socket = if rtcp_mux {
+            None
+        } else {
+            let rtcp_addr = SocketAddr::new(bind_ip, local_rtp_port + 1);
+            let rtcp_socket = LocalSocket {
+                socket: UdpSocket::bind(rtcp_addr).await?,
+                _lease: lease,
+            };
+            info!("RTCP socket bound to {}", rtcp_addr);
+            Some(Arc::new(rtcp_socket))
+        };
 
         let rtp_session = Arc::new(RtpSession::new(payload_type, clock_rate));
 
         Ok(Self {
-            rtp_session,
+            rtp_session: std::sync::RwLock::new(rtp_session),
+            payloads: Arc::new(std::sync::RwLock::new(PayloadMap::default())),
             rtp_socket: Arc::new(rtp_socket),
-            rtcp_socket: Arc::new(rtcp_socket),
+            rtcp_socket,
+            rtcp_mux: Arc::new(AtomicBool::new(rtcp_mux)),
+            rtcp_interval: RTCP_INTERVAL,
             remote_rtp: Arc::new(RwLock::new(None)),
             remote_rtcp: Arc::new(RwLock::new(None)),
+            sdp_remote_rtp: Mutex::new(None),
+            latch: Arc::new(Mutex::new(RtpLatch::default())),
+            decode_health: Arc::new(Mutex::new(DecodeHealth::default())),
+            comfort_noise: Arc::new(AtomicBool::new(false)),
+            bandwidth: None,
             direction: Arc::new(RwLock::new(StreamDirection::Inactive)),
             running: Arc::new(RwLock::new(false)),
             srtp_context: Arc::new(RwLock::new(None)),
+            tasks: Mutex::new(Vec::new()),
+            port_allocator: None,
+            packets_received: Arc::new(AtomicU32::new(0)),
+            bytes_received: Arc::new(AtomicU32::new(0)),
+            received: broadcast::channel(RECEIVED_PACKETS_CAPACITY).0,
+            last_received: Arc::new(Mutex::new(None)),
+            received_rtcp: broadcast::channel(RECEIVED_PACKETS_CAPACITY).0,
+            final_stats: Mutex::new(None),
+            closed: AtomicBool::new(false),
         })
     }
 
+    /// Create a media stream on a port pair from `allocator`
+    ///
+    /// The pair goes back to the allocator once the stream is dropped and
+    /// its sockets are closed. Pairs that fail to bind are released and the
+    /// next one is tried.
+    pub async fn allocate(
+        allocator: Arc<RtpPortAllocator>,
+        bind_ip: IpAddr,
+        payload_type: u8,
+        clock_rate: u32,
+    ) -> Result<Self, std::io::Error> {
+        Self::allocate_ports(allocator, bind_ip, payload_type, clock_rate, false).await
+    }
+
+    /// Create a media stream multiplexing RTCP on a single port from
+    /// `allocator`
+    pub async fn allocate_muxed(
+        allocator: Arc<RtpPortAllocator>,
+        bind_ip: IpAddr,
+        payload_type: u8,
+        clock_rate: u32,
+    ) -> Result<Self, std::io::Error> {
+        Self::allocate_ports(allocator, bind_ip, payload_type, clock_rate, true).await
+    }
+
+    async fn allocate_ports(
+        allocator: Arc<RtpPortAllocator>,
+        bind_ip: IpAddr,
+        payload_type: u8,
+        clock_rate: u32,
+        rtcp_mux: bool,
+    ) -> Result<Self, std::io::Error> {
+        let mut last_error = None;
+        for _ in 0..MAX_BIND_ATTEMPTS {
+            let port = if rtcp_mux {
+                allocator.allocate_single()
+            } else {
+                allocator.allocate()
+            }
+            .ok_or_else(|| {
+                std::io::Error::new(std::io::ErrorKind::AddrNotAvailable, "RTP port range exhausted")
+            })?;
+
+            // Dropping the lease on a failed bind releases the port
+            let lease = Arc::new(PortLease::new(allocator.clone(), port));
+            match Self::bind_ports(bind_ip, port, payload_type, clock_rate, rtcp_mux, Some(lease)).await {
+                Ok(mut stream) => {
+                    stream.port_allocator = Some(allocator);
+                    return Ok(stream);
+                }
+                Err(e) => {
+                    if e.kind() != std::io::ErrorKind::AddrInUse {
+                        return Err(e);
+                    }
+                    debug!("RTP port {} in use, trying the next one", port);
+                    last_error = Some(e);
+                }
+            }
+        }
+        Err(last_error.unwrap_or_else(|| {
+            std::io::Error::new(std::io::ErrorKind::AddrInUse, "No bindable RTP port")
+        }))
+    }
+
+    /// Send RTCP reports every `interval` instead of every 5 seconds
+    pub fn with_rtcp_interval(mut self, interval: Duration) -> Self {
+        self.rtcp_interval = interval;
+        self
+    }
+
+    /// Estimate the bandwidth of received media with `estimator` and send
+    /// the estimates as REMB
+    pub fn with_bandwidth_estimation(mut self, estimator: Arc<Mutex<BandwidthEstimator>>) -> Self {
+        self.bandwidth = Some(estimator);
+        self
+    }
+
+    /// Send with `session` instead of the stream's own, e.g. one continuing
+    /// a media session from before a restart
+    pub fn with_rtp_session(mut self, session: RtpSession) -> Self {
+        *self.rtp_session.get_mut().unwrap() = Arc::new(session);
+        self
+    }
+
+    fn rtp_session(&self) -> Arc<RtpSession> {
+        self.rtp_session.read().unwrap().clone()
+    }
+
+    /// Switch the payload type after codec negotiation
+    ///
+    /// Only valid before `start()`; the SSRC is kept.
+    pub async fn set_payload_type(&self, payload_type: u8) {
+        let old = self.rtp_session();
+        if old.payload_type() == payload_type {
+            return;
+        }
+        let session = Arc::new(RtpSession::with_ssrc(old.ssrc(), payload_type, old.clock_rate()));
+        *self.rtp_session.write().unwrap() = session;
+        old.close().await;
+    }
+
+    /// Use the payload types negotiated with the remote
+    ///
+    /// Media is sent with the first codec of the map. Only valid before
+    /// `start()`.
+    pub async fn set_payloads(&self, payloads: PayloadMap) {
+        if let Some(codec) = payloads.primary() {
+            self.set_payload_type(codec.payload_type).await;
+        }
+        *self.payloads.write().unwrap() = payloads;
+    }
+
+    /// Change codec mid-call after renegotiating it with the remote
+    ///
+    /// Unlike `set_payloads()`, the sequence numbers, timestamps and
+    /// counters of the media sent so far continue.
+    pub async fn switch_payloads(&self, payloads: PayloadMap) {
+        if let Some(codec) = payloads.primary() {
+            let old = self.rtp_session();
+            if old.payload_type() != codec.payload_type {
+                let session = old.continuing(codec.payload_type, codec.clock_rate);
+                *self.rtp_session.write().unwrap() = Arc::new(session);
+            }
+            info!("Media switched to {} (PT {})", codec.name, codec.payload_type);
+        }
+        *self.payloads.write().unwrap() = payloads;
+        self.decode_health.lock().unwrap().reset();
+    }
+
+    /// Payload types negotiated with the remote (empty until negotiated)
+    pub fn payloads(&self) -> PayloadMap {
+        self.payloads.read().unwrap().clone()
+    }
+
+    /// Payload type of the remote's telephone-events, if negotiated
+    pub fn telephone_event_payload_type(&self) -> Option<u8> {
+        self.payloads.read().unwrap().telephone_event
+    }
+
     /// Set remote addresses
+    ///
+    /// RTCP is sent to `rtp_addr` instead while multiplexed. A latched
+    /// address is kept while the SDP address stays the same.
     pub async fn set_remote(&self, rtp_addr: SocketAddr, rtcp_addr: SocketAddr) {
+        let previous = self.sdp_remote_rtp.lock().unwrap().replace(rtp_addr);
+        if previous == Some(rtp_addr) && self.latched_remote().is_some() {
+            debug!("Remote RTP {} unchanged, keeping the latched address", rtp_addr);
+            return;
+        }
+        self.latch.lock().unwrap().reset();
         *self.remote_rtp.write().await = Some(rtp_addr);
         *self.remote_rtcp.write().await = Some(rtcp_addr);
         info!("Remote RTP: {}, RTCP: {}", rtp_addr, rtcp_addr);
     }
 
+    /// Latch onto the address the remote's media comes from, or stop
+    pub fn set_latching(&self, config: LatchConfig) {
+        if config.enabled {
+            debug!("RTP latching enabled");
+        }
+        self.latch.lock().unwrap().set_config(config);
+    }
+
+    pub fn latching(&self) -> LatchConfig {
+        self.latch.lock().unwrap().config()
+    }
+
+    /// Remote RTP address from the SDP
+    pub fn sdp_remote(&self) -> Option<SocketAddr> {
+        *self.sdp_remote_rtp.lock().unwrap()
+    }
+
+    /// Address media is sent to instead of the SDP one, once latched
+    pub fn latched_remote(&self) -> Option<SocketAddr> {
+        self.latch.lock().unwrap().latched()
+    }
+
+    /// Share of received Opus packets lost to decode errors over the last
+    /// few seconds
+    pub fn decode_error_rate(&self) -> f64 {
+        self.decode_health
+            .lock()
+            .unwrap()
+            .error_rate(std::time::Instant::now())
+    }
+
+    pub fn decode_counters(&self) -> DecodeCounters {
+        self.decode_health.lock().unwrap().counters()
+    }
+
+    pub fn estimates_bandwidth(&self) -> bool {
+        self.bandwidth.is_some()
+    }
+
+    /// Current bandwidth estimates, with estimation on
+    pub fn bandwidth_estimate(&self) -> Option<BandwidthEstimate> {
+        self.bandwidth
+            .as_ref()
+            .map(|estimator| estimator.lock().unwrap().estimate())
+    }
+
+    /// Pass undecodable Opus packets on as comfort noise, or as received
+    pub fn set_comfort_noise(&self, enabled: bool) {
+        self.comfort_noise.store(enabled, Ordering::Relaxed);
+    }
+
+    pub fn comfort_noise(&self) -> bool {
+        self.comfort_noise.load(Ordering::Relaxed)
+    }
+
     /// Set stream direction
     pub async fn set_direction(&self, direction: StreamDirection) {
         *self.direction.write().await = direction;
@@ -87,7 +477,7 @@ impl MediaStream {
 
     /// Get SSRC
     pub fn ssrc(&self) -> u32 {
-        self.rtp_session.ssrc()
+        self.rtp_session().ssrc()
     }
 
     /// Get local RTP port
@@ -95,6 +485,36 @@ impl MediaStream {
         Ok(self.rtp_socket.local_addr()?.port())
     }
 
+    /// Local RTCP port, `None` while RTCP is multiplexed on the RTP port
+    pub fn local_rtcp_port(&self) -> Option<u16> {
+        if self.is_rtcp_muxed() {
+            return None;
+        }
+        let socket = self.rtcp_socket.as_ref()?;
+        socket.local_addr().ok().map(|addr| addr.port())
+    }
+
+    /// Whether RTCP is multiplexed on the RTP port
+    pub fn is_rtcp_muxed(&self) -> bool {
+        self.rtcp_mux.load(Ordering::Relaxed)
+    }
+
+    /// Multiplex RTCP on the RTP port or not, as negotiated with the remote
+    ///
+    /// A stream created muxed has no RTCP port to fall back to.
+    pub fn set_rtcp_mux(&self, enabled: bool) -> Result<(), std::io::Error> {
+        if !enabled && self.rtcp_socket.is_none() {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::Unsupported,
+                "Media stream has no RTCP port",
+            ));
+        }
+        if self.rtcp_mux.swap(enabled, Ordering::Relaxed) != enabled {
+            info!("RTCP multiplexing {}", if enabled { "enabled" } else { "disabled" });
+        }
+        Ok(())
+    }
+
     /// Enable SRTP encryption
     pub async fn enable_srtp(&self, master_key: SrtpMasterKey, profile: SrtpProfile) {
         let crypto_ctx = MediaCryptoContext::new(master_key, profile);
@@ -102,6 +522,25 @@ impl MediaStream {
         info!("SRTP enabled with profile: {:?}", profile);
     }
 
+    /// Switch SRTP to a new master key, still decrypting packets protected
+    /// with the old one for `overlap`. False when SRTP is not enabled.
+    pub async fn rekey_srtp(
+        &self,
+        master_key: SrtpMasterKey,
+        profile: SrtpProfile,
+        overlap: Duration,
+    ) -> bool {
+        let mut context = self.srtp_context.write().await;
+        match context.take() {
+            Some(current) => {
+                *context = Some(current.rekey(master_key, profile, overlap));
+                info!("SRTP re-keyed with profile: {:?}", profile);
+                true
+            }
+            None => false,
+        }
+    }
+
     /// Disable SRTP encryption
     pub async fn disable_srtp(&self) {
         *self.srtp_context.write().await = None;
@@ -120,8 +559,8 @@ impl MediaStream {
             return Ok(()); // Can't send in recv-only or inactive mode
         }
 
-        let packet = self.rtp_session.create_packet(payload, timestamp, marker);
-        let mut data = packet.serialize();
+        let packet = self.rtp_session().create_packet(payload, timestamp, marker);
+        let mut data = packet.serialize().to_vec();
 
         // Apply SRTP encryption if enabled
         if let Some(ref ctx) = *self.srtp_context.read().await {
@@ -147,115 +586,428 @@ impl MediaStream {
 
     /// Start receiving RTP packets
     pub async fn start(&self) -> Result<(), std::io::Error> {
+        if self.closed.load(Ordering::SeqCst) {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::NotConnected,
+                "Media stream is closed",
+            ));
+        }
         *self.running.write().await = true;
 
-        // Spawn RTP receiver task
+        let mut tasks = self.tasks.lock().unwrap();
+
+        // Spawn RTP receiver task, also receiving RTCP
         let rtp_socket = self.rtp_socket.clone();
+        let rtcp_socket = self.rtcp_socket.clone();
+        let rtcp_mux = self.rtcp_mux.clone();
         let direction = self.direction.clone();
         let running = self.running.clone();
         let srtp_context = self.srtp_context.clone();
+        let packets_received = self.packets_received.clone();
+        let bytes_received = self.bytes_received.clone();
+        let received = self.received.clone();
+        let last_received = self.last_received.clone();
+        let received_rtcp = self.received_rtcp.clone();
+        let latch = self.latch.clone();
+        let remote_rtp = self.remote_rtp.clone();
+        let remote_rtcp = self.remote_rtcp.clone();
+        let payloads = self.payloads.clone();
+        let decode_health = self.decode_health.clone();
+        let comfort_noise = self.comfort_noise.clone();
+        let bandwidth = self.bandwidth.clone();
+        let local_ssrc = self.rtp_session().ssrc();
+        // Until negotiated, only our own payload type is expected
+        let default_payload_type = self.rtp_session().payload_type();
+        let tracker = self.port_allocator.as_ref().map(|a| a.track_task());
 
-        tokio::spawn(async move {
+        tasks.push(tokio::spawn(async move {
+            let _tracker = tracker;
             let mut buf = vec![0u8; 2048];
 
             while *running.read().await {
-                let dir = *direction.read().await;
-                if dir == StreamDirection::SendOnly || dir == StreamDirection::Inactive {
-                    tokio::time::sleep(Duration::from_millis(100)).await;
+                let ready = tokio::select! {
+                    ready = rtp_socket.readable() => ready.map(|_| false),
+                    ready = readable(rtcp_socket.as_deref()) => ready.map(|_| true),
+                };
+                let (socket, on_rtcp_port) = match (ready, &rtcp_socket) {
+                    (Ok(true), Some(rtcp_socket)) => (rtcp_socket, true),
+                    (Ok(_), _) => (&rtp_socket, false),
+                    (Err(e), _) => {
+                        error!("RTP recv error: {}", e);
+                        continue;
+                    }
+                };
+                let (len, addr) = match socket.try_recv_from(&mut buf) {
+                    Ok(received) => received,
+                    Err(e) if e.kind() == std::io::ErrorKind::WouldBlock => continue,
+                    Err(e) => {
+                        if *running.read().await {
+                            error!("RTP recv error: {}", e);
+                        }
+                        continue;
+                    }
+                };
+
+                let muxed_rtcp = !on_rtcp_port
+                    && rtcp_mux.load(Ordering::Relaxed)
+                    && RtpSession::demux(&buf[..len]) == Some(MuxedPacket::Rtcp);
+                if on_rtcp_port || muxed_rtcp {
+                    match RtcpPacket::parse_compound(&buf[..len]) {
+                        Ok(packets) => {
+                            debug!("Received RTCP packet from {}: {} bytes", addr, len);
+                            // The NAT maps the RTCP port of a latched remote
+                            // separately
+                            let latched = latch.lock().unwrap().latched();
+                            if on_rtcp_port && latched.is_some_and(|l| l.ip() == addr.ip()) {
+                                let mut remote = remote_rtcp.write().await;
+                                if *remote != Some(addr) {
+                                    info!("Latched remote RTCP to {}", addr);
+                                    *remote = Some(addr);
+                                }
+                            }
+                            for packet in packets {
+                                if let Some(bandwidth) = &bandwidth {
+                                    let reports = match &packet {
+                                        RtcpPacket::SenderReport(sr) => &sr.reports[..],
+                                        RtcpPacket::ReceiverReport(rr) => &rr.reports[..],
+                                        _ => &[],
+                                    };
+                                    for report in reports.iter().filter(|r| r.ssrc == local_ssrc) {
+                                        bandwidth.lock().unwrap().on_report(report.fraction_lost);
+                                    }
+                                }
+                                // No subscriber is not an error
+                                let _ = received_rtcp.send(packet);
+                            }
+                        }
+                        Err(e) => {
+                            warn!("Failed to parse RTCP packet from {}: {}", addr, e);
+                        }
+                    }
                     continue;
                 }
 
-                match rtp_socket.recv_from(&mut buf).await {
-                    Ok((len, addr)) => {
-                        debug!("Received RTP packet from {}: {} bytes", addr, len);
+                if !direction.read().await.receives() {
+                    continue;
+                }
 
-                        let mut packet_data = buf[..len].to_vec();
+                debug!("Received RTP packet from {}: {} bytes", addr, len);
+                packets_received.fetch_add(1, Ordering::Relaxed);
+                bytes_received.fetch_add(len as u32, Ordering::Relaxed);
 
-                        // Apply SRTP decryption if enabled
-                        if let Some(ref ctx) = *srtp_context.read().await {
-                            if let Err(e) = ctx.unprotect_rtp(&mut packet_data) {
-                                warn!("SRTP decryption failed: {}", e);
-                                continue;
-                            }
-                            debug!("Decrypted RTP packet with SRTP");
-                        }
+                let mut packet_data = buf[..len].to_vec();
 
-                        match RtpPacket::parse(&packet_data) {
-                            Ok(packet) => {
-                                debug!("Parsed RTP: {}", packet);
-                                // TODO: Process received packet (decode, play, etc.)
-                            }
-                            Err(e) => {
-                                warn!("Failed to parse RTP packet: {}", e);
+                // Apply SRTP decryption if enabled
+                if let Some(ref ctx) = *srtp_context.read().await {
+                    if let Err(e) = ctx.unprotect_rtp(&mut packet_data) {
+                        warn!("SRTP decryption failed: {}", e);
+                        continue;
+                    }
+                    debug!("Decrypted RTP packet with SRTP");
+                }
+
+                match RtpPacket::parse(&packet_data) {
+                    Ok(mut packet) => {
+                        debug!("Parsed RTP: {}", packet);
+                        // A packet of a type not negotiated is no evidence
+                        // of the remote's address
+                        let (payload_types, opus_fec, clock_rate) = {
+                            let payloads = payloads.read().unwrap();
+                            let opus_fec = payloads
+                                .codec(packet.payload_type)
+                                .filter(|codec| codec.name.eq_ignore_ascii_case("opus"))
+                                .map(|_| payloads.opus.as_ref().is_some_and(|o| o.fec_enabled));
+                            let clock_rate = payloads
+                                .codec(packet.payload_type)
+                                .map_or(8000, |codec| codec.clock_rate);
+                            (payloads.payload_types(), opus_fec, clock_rate)
+                        };
+                        let payload_types = if payload_types.is_empty() {
+                            vec![default_payload_type]
+                        } else {
+                            payload_types
+                        };
+                        let relatched = latch.lock().unwrap().observe(addr, &packet, &payload_types);
+                        if let Some(latched) = relatched {
+                            info!("Latched remote RTP to {}", latched);
+                            *remote_rtp.write().await = Some(latched);
+                        }
+                        *last_received.lock().unwrap() = Some((packet.ssrc, packet.sequence));
+                        if let Some(bandwidth) = &bandwidth {
+                            bandwidth.lock().unwrap().on_rtp(
+                                std::time::Instant::now(),
+                                &packet,
+                                clock_rate,
+                            );
+                        }
+                        if let Some(fec) = opus_fec {
+                            let decodes = decode_health.lock().unwrap().observe(
+                                std::time::Instant::now(),
+                                packet.sequence,
+                                &packet.payload,
+                                fec,
+                            );
+                            if !decodes && comfort_noise.load(Ordering::Relaxed) {
+                                packet = comfort_noise_packet(&packet);
                             }
                         }
+                        // No subscriber is not an error
+                        let _ = received.send(packet);
                     }
                     Err(e) => {
-                        if *running.read().await {
-                            error!("RTP recv error: {}", e);
-                        }
+                        warn!("Failed to parse RTP packet: {}", e);
                     }
                 }
             }
 
             info!("RTP receiver stopped");
-        });
+        }));
 
         // Spawn RTCP sender task
+        let rtp_socket = self.rtp_socket.clone();
         let rtcp_socket = self.rtcp_socket.clone();
-        let rtp_session = self.rtp_session.clone();
+        let rtcp_mux = self.rtcp_mux.clone();
+        let rtp_session = self.rtp_session();
+        let remote_rtp = self.remote_rtp.clone();
         let remote_rtcp = self.remote_rtcp.clone();
+        let last_received = self.last_received.clone();
         let running = self.running.clone();
+        let rtcp_interval = self.rtcp_interval;
+        let bandwidth = self.bandwidth.clone();
+        let tracker = self.port_allocator.as_ref().map(|a| a.track_task());
 
-        tokio::spawn(async move {
-            let mut timer = interval(Duration::from_secs(5));
+        tasks.push(tokio::spawn(async move {
+            let _tracker = tracker;
+            let mut timer = interval(rtcp_interval);
 
             while *running.read().await {
                 timer.tick().await;
 
-                if let Some(remote) = *remote_rtcp.read().await {
-                    // Send Sender Report
-                    let sr = SenderReport::new(
+                // Multiplexed RTCP goes to the remote's RTP port
+                let (socket, remote) = match &rtcp_socket {
+                    Some(rtcp_socket) if !rtcp_mux.load(Ordering::Relaxed) => {
+                        (rtcp_socket, *remote_rtcp.read().await)
+                    }
+                    _ => (&rtp_socket, *remote_rtp.read().await),
+                };
+                let Some(remote) = remote else {
+                    continue;
+                };
+
+                let report = (*last_received.lock().unwrap()).map(|(ssrc, sequence)| {
+                    let mut report = ReceptionReport::new(ssrc);
+                    report.highest_seq = sequence as u32;
+                    report
+                });
+                // Sender Report once we sent media, Receiver Report before
+                // (RFC 3550 section 6.4)
+                let data = if rtp_session.packets_sent() > 0 {
+                    let mut sr = SenderReport::new(
                         rtp_session.ssrc(),
                         0, // timestamp
                         rtp_session.packets_sent(),
                         rtp_session.bytes_sent(),
                     );
+                    if let Some(report) = report {
+                        sr.add_report(report);
+                    }
+                    sr.serialize()
+                } else {
+                    let mut rr = ReceiverReport::new(rtp_session.ssrc());
+                    if let Some(report) = report {
+                        rr.add_report(report);
+                    }
+                    rr.serialize()
+                };
+                // Compound with the report (RFC 4585 section 3.1)
+                let remb = bandwidth.as_ref().and_then(|estimator| {
+                    estimator
+                        .lock()
+                        .unwrap()
+                        .update(std::time::Instant::now(), rtp_session.ssrc())
+                });
+                let data = match remb {
+                    Some(remb) => [&data[..], &remb.serialize()[..]].concat(),
+                    None => data.to_vec(),
+                };
 
-                    let data = sr.serialize();
-
-                    match rtcp_socket.send_to(&data, remote).await {
-                        Ok(_) => {
-                            debug!("Sent RTCP SR to {}", remote);
-                        }
-                        Err(e) => {
-                            warn!("Failed to send RTCP SR: {}", e);
-                        }
+                match socket.send_to(&data, remote).await {
+                    Ok(_) => {
+                        debug!("Sent RTCP report to {}", remote);
+                    }
+                    Err(e) => {
+                        warn!("Failed to send RTCP report: {}", e);
                     }
                 }
             }
 
             info!("RTCP sender stopped");
-        });
+        }));
 
         info!("Media stream started");
         Ok(())
     }
 
+    /// Packets received from now on, after SRTP decryption
+    pub fn subscribe(&self) -> broadcast::Receiver<RtpPacket> {
+        self.received.subscribe()
+    }
+
+    /// RTCP packets received from now on, on either port
+    pub fn subscribe_rtcp(&self) -> broadcast::Receiver<RtcpPacket> {
+        self.received_rtcp.subscribe()
+    }
+
     /// Stop the stream
+    ///
+    /// Tasks blocked on a socket only notice on the next packet; use
+    /// `close()` to release the stream.
     pub async fn stop(&self) {
         *self.running.write().await = false;
         info!("Media stream stopped");
     }
+
+    /// Packet counters; the final snapshot once closed
+    pub fn stats(&self) -> RtpStats {
+        if let Some(stats) = self.final_stats.lock().unwrap().as_ref() {
+            return stats.clone();
+        }
+        RtpStats {
+            packets_received: self.packets_received.load(Ordering::Relaxed),
+            bytes_received: self.bytes_received.load(Ordering::Relaxed),
+            ..self.rtp_session().stats()
+        }
+    }
+
+    /// Whether `close()` was called
+    pub fn is_closed(&self) -> bool {
+        self.closed.load(Ordering::SeqCst)
+    }
+
+    /// Stop the tasks and freeze the statistics
+    ///
+    /// The port pair goes back to the allocator when the stream is dropped.
+    /// Safe to call more than once.
+    pub async fn close(&self) {
+        if self.is_closed() {
+            return;
+        }
+        *self.running.write().await = false;
+        self.rtp_session().close().await;
+        // Wait for the aborted tasks so their socket clones are gone
+        let tasks: Vec<_> = self.tasks.lock().unwrap().drain(..).collect();
+        for task in &tasks {
+            task.abort();
+        }
+        for task in tasks {
+            let _ = task.await;
+        }
+        self.release();
+        info!("Media stream closed");
+    }
+
+    /// Synchronous part of `close()`, also used on drop
+    pub(crate) fn release(&self) {
+        if self.closed.swap(true, Ordering::SeqCst) {
+            return;
+        }
+        if let Ok(mut running) = self.running.try_write() {
+            *running = false;
+        }
+
+        for task in self.tasks.lock().unwrap().drain(..) {
+            task.abort();
+        }
+
+        *self.final_stats.lock().unwrap() = Some(RtpStats {
+            packets_received: self.packets_received.load(Ordering::Relaxed),
+            bytes_received: self.bytes_received.load(Ordering::Relaxed),
+            ..self.rtp_session().stats()
+        });
+    }
 }
 
 impl Drop for MediaStream {
     fn drop(&mut self) {
-        // Ensure stream is stopped
-        let running = self.running.clone();
-        tokio::spawn(async move {
-            *running.write().await = false;
-        });
+        if !self.is_closed() {
+            warn!(
+                "Media stream on port {:?} dropped without close()",
+                self.rtp_socket.local_addr().map(|a| a.port()).ok()
+            );
+            self.release();
+        }
+    }
+}
+
+/// A bound socket holding its port's lease
+///
+/// The socket is declared first so it is closed before the lease gives
+/// the port back to the allocator.
+struct LocalSocket {
+    socket: UdpSocket,
+    _lease: Option<Arc<PortLease>>,
+}
+
+impl std::ops::Deref for LocalSocket {
+    type Target = UdpSocket;
+
+    fn deref(&self) -> &UdpSocket {
+        &self.socket
+    }
+}
+
+/// Readiness of an optional socket; never ready without one
+async fn readable(socket: Option<&LocalSocket>) -> std::io::Result<()> {
+    match socket {
+        Some(socket) => socket.readable().await,
+        None => std::future::pending().await,
+    }
+}
+
+/// Comfort noise standing in for an undecodable packet, in its place in
+/// the stream
+fn comfort_noise_packet(packet: &RtpPacket) -> RtpPacket {
+    RtpPacket::new(
+        COMFORT_NOISE_PT,
+        packet.sequence,
+        packet.timestamp,
+        packet.ssrc,
+        Bytes::from_static(&[COMFORT_NOISE_LEVEL]),
+    )
+}
+
+/// Closes a media stream when dropped, unless disarmed
+///
+/// Hold one while setting up a call so every early return releases the
+/// stream's tasks and ports.
+pub struct MediaStreamGuard {
+    stream: Option<Arc<MediaStream>>,
+}
+
+impl MediaStreamGuard {
+    pub fn new(stream: Arc<MediaStream>) -> Self {
+        Self {
+            stream: Some(stream),
+        }
+    }
+
+    /// The guarded stream
+    pub fn stream(&self) -> &Arc<MediaStream> {
+        self.stream.as_ref().expect("guard already disarmed")
+    }
+
+    /// Keep the stream open and hand it over
+    pub fn disarm(mut self) -> Arc<MediaStream> {
+        self.stream.take().expect("guard already disarmed")
+    }
+}
+
+impl Drop for MediaStreamGuard {
+    fn drop(&mut self) {
+        if let Some(stream) = self.stream.take() {
+            debug!("Closing media stream abandoned during call setup");
+            stream.release();
+        }
     }
 }
 
@@ -281,6 +1033,65 @@ mod tests {
         assert!(stream.remote_rtcp.read().await.is_some());
     }
 
+    #[tokio::test]
+    async fn test_close_releases_port_and_tasks() {
+        let allocator = Arc::new(RtpPortAllocator::new(31000, 31010));
+        let stream = MediaStream::allocate(allocator.clone(), IpAddr::V4(Ipv4Addr::LOCALHOST), 0, 8000)
+            .await
+            .unwrap();
+        let port = stream.local_rtp_port().unwrap();
+        assert!(allocator.is_allocated(port));
+
+        stream.start().await.unwrap();
+        assert_eq!(allocator.live_tasks(), 2);
+
+        stream.close().await;
+        stream.close().await;
+        assert_eq!(allocator.live_tasks(), 0);
+        assert!(stream.start().await.is_err());
+        // The port stays reserved while the stream still holds its sockets
+        assert!(allocator.is_allocated(port));
+        drop(stream);
+        assert!(!allocator.is_allocated(port));
+
+        // Dropping an unclosed stream still gives the port back
+        let dropped = MediaStream::allocate(allocator.clone(), IpAddr::V4(Ipv4Addr::LOCALHOST), 0, 8000)
+            .await
+            .unwrap();
+        assert_eq!(allocator.in_use(), 1);
+        drop(dropped);
+        assert_eq!(allocator.in_use(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_muxed_stream_takes_single_port() {
+        let allocator = Arc::new(RtpPortAllocator::new(31020, 31030));
+        let stream =
+            MediaStream::allocate_muxed(allocator.clone(), IpAddr::V4(Ipv4Addr::LOCALHOST), 0, 8000)
+                .await
+                .unwrap();
+        let port = stream.local_rtp_port().unwrap();
+        assert!(stream.is_rtcp_muxed());
+        assert_eq!(stream.local_rtcp_port(), None);
+        assert!(allocator.is_allocated(port));
+        assert!(!allocator.is_allocated(port + 1));
+        assert!(stream.set_rtcp_mux(false).is_err());
+
+        // A paired stream falls back to RTP+1 when mux is not negotiated
+        let paired = MediaStream::allocate(allocator.clone(), IpAddr::V4(Ipv4Addr::LOCALHOST), 0, 8000)
+            .await
+            .unwrap();
+        paired.set_rtcp_mux(true).unwrap();
+        assert_eq!(paired.local_rtcp_port(), None);
+        paired.set_rtcp_mux(false).unwrap();
+        assert_eq!(paired.local_rtcp_port(), Some(paired.local_rtp_port().unwrap() + 1));
+
+        stream.close().await;
+        paired.close().await;
+        drop((stream, paired));
+        assert_eq!(allocator.in_use(), 0);
+    }
+
     #[tokio::test]
     async fn test_stream_direction() {
         let stream = MediaStream::new(10004, 0, 8000).await.unwrap();
@@ -288,4 +1099,63 @@ mod tests {
 
         assert_eq!(*stream.direction.read().await, StreamDirection::SendRecv);
     }
+
+    #[tokio::test]
+    async fn test_media_follows_natted_sender() {
+        use super::super::latch::DEFAULT_CONFIRM_PACKETS;
+
+        let stream = MediaStream::bind(IpAddr::V4(Ipv4Addr::LOCALHOST), 10006, 0, 8000)
+            .await
+            .unwrap();
+        stream.set_direction(StreamDirection::SendRecv).await;
+        // The phone's SDP has its private address; its media comes from the NAT
+        let private: SocketAddr = "192.168.1.50:4000".parse().unwrap();
+        let private_rtcp: SocketAddr = "192.168.1.50:4001".parse().unwrap();
+        stream.set_remote(private, private_rtcp).await;
+        stream.set_latching(LatchConfig::for_leg(private.ip(), Some("203.0.113.7".parse().unwrap())));
+        stream.start().await.unwrap();
+
+        let nat = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let nat_addr = nat.local_addr().unwrap();
+        let phone = RtpSession::with_ssrc(0x5eed, 0, 8000);
+        let send = |i: u32| {
+            let packet = phone.create_packet(Bytes::from_static(&[0xff; 160]), i * 160, false);
+            packet.serialize()
+        };
+        for i in 0..DEFAULT_CONFIRM_PACKETS - 1 {
+            nat.send_to(&send(i), ("127.0.0.1", 10006)).await.unwrap();
+        }
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert_eq!(stream.latched_remote(), None);
+
+        nat.send_to(&send(DEFAULT_CONFIRM_PACKETS), ("127.0.0.1", 10006))
+            .await
+            .unwrap();
+        tokio::time::timeout(Duration::from_secs(2), async {
+            while stream.latched_remote() != Some(nat_addr) {
+                tokio::time::sleep(Duration::from_millis(5)).await;
+            }
+        })
+        .await
+        .unwrap();
+        assert_eq!(stream.sdp_remote(), Some(private));
+
+        // Our media now reaches the phone through its NAT
+        stream
+            .send_rtp(Bytes::from_static(&[0x7f; 160]), 0, false)
+            .await
+            .unwrap();
+        let mut buf = [0u8; 2048];
+        let (len, _) = tokio::time::timeout(Duration::from_secs(2), nat.recv_from(&mut buf))
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(RtpPacket::parse(&buf[..len]).unwrap().ssrc, stream.ssrc());
+
+        // A re-INVITE repeating the SDP address keeps the learned one
+        stream.set_remote(private, private_rtcp).await;
+        assert_eq!(stream.latched_remote(), Some(nat_addr));
+        assert_eq!(*stream.remote_rtp.read().await, Some(nat_addr));
+        stream.close().await;
+    }
 }
@@ -0,0 +1,184 @@
+//! Decoded audio of a bridged call leg, for consumers beside the call
+//!
+//! A [`MediaTap`] added to a [`MediaBridge`](super::MediaBridge) is offered
+//! every packet the bridge forwards from its leg. G.711 payloads are decoded
+//! and queued for the tap's [`PcmReceiver`]; other payloads (telephone
+//! events, comfort noise) are ignored. The queue is bounded and drops its
+//! oldest chunk when full, so a slow reader never holds up the media path.
+
+use super::bridge::BridgeLeg;
+use super::codec::G711Type;
+use super::rtp::RtpPacket;
+use futures::stream::{self, BoxStream, StreamExt};
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
+use tokio::sync::Notify;
+
+/// Chunks a tap holds by default: 5 s of 20 ms packets
+pub const DEFAULT_TAP_CHUNKS: usize = 250;
+
+struct Queue {
+    chunks: VecDeque<Vec<i16>>,
+    capacity: usize,
+    /// Set when either end is done
+    closed: bool,
+    dropped: u64,
+}
+
+struct Shared {
+    queue: Mutex<Queue>,
+    notify: Notify,
+}
+
+impl Shared {
+    fn close(&self) {
+        self.queue.lock().unwrap().closed = true;
+        self.notify.notify_one();
+    }
+}
+
+/// Sending end of a tap, held by the bridge
+#[derive(Clone)]
+pub struct MediaTap {
+    leg: BridgeLeg,
+    shared: Arc<Shared>,
+}
+
+/// Receiving end of a tap
+pub struct PcmReceiver {
+    shared: Arc<Shared>,
+}
+
+/// Tap on `leg` holding up to `capacity` chunks of PCM
+pub fn media_tap(leg: BridgeLeg, capacity: usize) -> (MediaTap, PcmReceiver) {
+    let shared = Arc::new(Shared {
+        queue: Mutex::new(Queue {
+            chunks: VecDeque::new(),
+            capacity: capacity.max(1),
+            closed: false,
+            dropped: 0,
+        }),
+        notify: Notify::new(),
+    });
+    (
+        MediaTap {
+            leg,
+            shared: shared.clone(),
+        },
+        PcmReceiver { shared },
+    )
+}
+
+impl MediaTap {
+    /// Leg whose received audio the tap takes
+    pub fn leg(&self) -> BridgeLeg {
+        self.leg
+    }
+
+    /// Queue the audio of a packet received on the tap's leg; never waits
+    pub fn offer(&self, packet: &RtpPacket) {
+        let Some(codec) = G711Type::from_payload_type(packet.payload_type) else {
+            return;
+        };
+        if packet.payload.is_empty() {
+            return;
+        }
+        let pcm = codec.decode(&packet.payload);
+        let mut queue = self.shared.queue.lock().unwrap();
+        if queue.closed {
+            return;
+        }
+        if queue.chunks.len() == queue.capacity {
+            queue.chunks.pop_front();
+            queue.dropped += 1;
+        }
+        queue.chunks.push_back(pcm);
+        drop(queue);
+        self.shared.notify.notify_one();
+    }
+
+    /// Stop feeding the receiver; it gets what is queued, then the end
+    pub fn close(&self) {
+        self.shared.close();
+    }
+
+    /// Whether either end is done, so the bridge can let go of the tap
+    pub fn is_closed(&self) -> bool {
+        self.shared.queue.lock().unwrap().closed
+    }
+}
+
+impl PcmReceiver {
+    /// Next chunk, or None once the tap is closed and drained
+    pub async fn recv(&self) -> Option<Vec<i16>> {
+        loop {
+            {
+                let mut queue = self.shared.queue.lock().unwrap();
+                if let Some(chunk) = queue.chunks.pop_front() {
+                    return Some(chunk);
+                }
+                if queue.closed {
+                    return None;
+                }
+            }
+            self.shared.notify.notified().await;
+        }
+    }
+
+    /// Chunks dropped because the reader fell behind
+    pub fn dropped(&self) -> u64 {
+        self.shared.queue.lock().unwrap().dropped
+    }
+
+    /// The chunks as a stream
+    pub fn into_stream(self) -> BoxStream<'static, Vec<i16>> {
+        stream::unfold(self, |receiver| async move {
+            let chunk = receiver.recv().await?;
+            Some((chunk, receiver))
+        })
+        .boxed()
+    }
+}
+
+impl Drop for PcmReceiver {
+    fn drop(&mut self) {
+        self.shared.close();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bytes::Bytes;
+
+    fn packet(sample: i16) -> RtpPacket {
+        RtpPacket::new(0, 1, 0, 0x1234, G711Type::PCMU.encode(&[sample; 160]))
+    }
+
+    #[tokio::test]
+    async fn test_full_tap_drops_oldest() {
+        let (tap, receiver) = media_tap(BridgeLeg::A, 2);
+        tap.offer(&packet(1000));
+        tap.offer(&packet(-1000));
+        tap.offer(&packet(2000));
+        // Not audio
+        tap.offer(&RtpPacket::new(101, 2, 0, 0x1234, Bytes::from_static(&[1, 0, 0, 160])));
+        tap.close();
+
+        assert_eq!(receiver.dropped(), 1);
+        let first = receiver.recv().await.unwrap();
+        assert_eq!(first.len(), 160);
+        assert!(first[0] < 0);
+        assert!(receiver.recv().await.unwrap()[0] > 1000);
+        assert!(receiver.recv().await.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_dropped_receiver_closes_tap() {
+        let (tap, receiver) = media_tap(BridgeLeg::B, 4);
+        assert!(!tap.is_closed());
+        drop(receiver);
+        assert!(tap.is_closed());
+        tap.offer(&packet(1000));
+    }
+}
@@ -0,0 +1,56 @@
+//! In-process event bus on a tokio broadcast channel
+
+use crate::application::events::{EventBus, EventEnvelope};
+use async_trait::async_trait;
+use std::sync::Mutex;
+use tokio::sync::broadcast;
+
+/// Default number of events buffered for slow subscribers
+pub const DEFAULT_EVENT_BUS_CAPACITY: usize = 1024;
+
+/// Delivers events to subscribers of this process, at most once
+///
+/// Events are dropped when nobody is subscribed; a subscriber that falls
+/// more than the channel capacity behind misses the oldest events.
+pub struct InProcessEventBus {
+    tx: broadcast::Sender<EventEnvelope>,
+    /// Keeps a batch together when several publishers race
+    send_lock: Mutex<()>,
+}
+
+impl InProcessEventBus {
+    pub fn new(capacity: usize) -> Self {
+        let (tx, _) = broadcast::channel(capacity);
+        Self {
+            tx,
+            send_lock: Mutex::new(()),
+        }
+    }
+
+    /// Number of active subscribers
+    pub fn subscriber_count(&self) -> usize {
+        self.tx.receiver_count()
+    }
+}
+
+impl Default for InProcessEventBus {
+    fn default() -> Self {
+        Self::new(DEFAULT_EVENT_BUS_CAPACITY)
+    }
+}
+
+#[async_trait]
+impl EventBus for InProcessEventBus {
+    async fn publish(&self, events: Vec<EventEnvelope>) -> Result<(), String> {
+        let _guard = self.send_lock.lock().unwrap();
+        for event in events {
+            // Ignore send errors (no subscribers)
+            let _ = self.tx.send(event);
+        }
+        Ok(())
+    }
+
+    fn subscribe(&self) -> broadcast::Receiver<EventEnvelope> {
+        self.tx.subscribe()
+    }
+}
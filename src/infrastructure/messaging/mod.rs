@@ -1,3 +1,9 @@
 //! Event bus and messaging implementations
 
-// Placeholder for messaging implementations
+pub mod in_process;
+#[cfg(feature = "postgres")]
+pub mod outbox;
+
+pub use in_process::{InProcessEventBus, DEFAULT_EVENT_BUS_CAPACITY};
+#[cfg(feature = "postgres")]
+pub use outbox::PgOutboxEventBus;
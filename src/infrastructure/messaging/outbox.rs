@@ -0,0 +1,144 @@
+//! Transactional outbox for at-least-once event delivery
+//!
+//! Published events are first stored in the `event_outbox` table; a relay
+//! task hands undelivered rows, oldest first, to in-process subscribers and
+//! only then marks them delivered. Events survive a crash or restart
+//! between the state change and delivery, at the cost of possibly being
+//! delivered twice: subscribers that must not apply an event twice
+//! de-duplicate on `event_id`.
+
+use super::in_process::InProcessEventBus;
+use crate::application::events::{EventBus, EventEnvelope};
+use async_trait::async_trait;
+use sqlx::{PgPool, Row};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::{broadcast, Notify};
+use tokio::task::JoinHandle;
+use tracing::{debug, error, warn};
+
+/// Rows relayed per query
+const RELAY_BATCH: i64 = 100;
+
+/// Event bus backed by the `event_outbox` table
+pub struct PgOutboxEventBus {
+    pool: PgPool,
+    relay: InProcessEventBus,
+    /// Wakes the relay as soon as something was stored
+    pending: Notify,
+}
+
+impl PgOutboxEventBus {
+    pub fn new(pool: PgPool) -> Self {
+        Self {
+            pool,
+            relay: InProcessEventBus::default(),
+            pending: Notify::new(),
+        }
+    }
+
+    /// Deliver stored events to subscribers, checking at least every
+    /// `poll_interval` for rows left by a previous run
+    pub fn spawn_relay(self: Arc<Self>, poll_interval: Duration) -> JoinHandle<()> {
+        tokio::spawn(async move {
+            loop {
+                match self.relay_pending().await {
+                    // More may be waiting
+                    Ok(count) if count as i64 == RELAY_BATCH => continue,
+                    Ok(_) => {}
+                    Err(e) => warn!("Event outbox relay failed: {}", e),
+                }
+                tokio::select! {
+                    _ = self.pending.notified() => {}
+                    _ = tokio::time::sleep(poll_interval) => {}
+                }
+            }
+        })
+    }
+
+    /// Publish one batch of undelivered events; returns the rows handled
+    async fn relay_pending(&self) -> Result<usize, String> {
+        let rows = sqlx::query(
+            r#"
+            SELECT id, payload FROM event_outbox
+            WHERE delivered_at IS NULL
+            ORDER BY id
+            LIMIT $1
+            "#,
+        )
+        .bind(RELAY_BATCH)
+        .fetch_all(&self.pool)
+        .await
+        .map_err(|e| format!("Database error: {}", e))?;
+        if rows.is_empty() {
+            return Ok(0);
+        }
+
+        let mut ids = Vec::with_capacity(rows.len());
+        let mut events = Vec::with_capacity(rows.len());
+        for row in rows {
+            let id: i64 = row.get("id");
+            let payload: serde_json::Value = row.get("payload");
+            match serde_json::from_value::<EventEnvelope>(payload) {
+                Ok(event) => events.push(event),
+                Err(e) => error!("Dropping undecodable outbox event {}: {}", id, e),
+            }
+            ids.push(id);
+        }
+
+        self.relay.publish(events).await?;
+        sqlx::query("UPDATE event_outbox SET delivered_at = NOW() WHERE id = ANY($1)")
+            .bind(&ids)
+            .execute(&self.pool)
+            .await
+            .map_err(|e| format!("Database error: {}", e))?;
+        debug!("Relayed {} outbox events", ids.len());
+        Ok(ids.len())
+    }
+}
+
+#[async_trait]
+impl EventBus for PgOutboxEventBus {
+    async fn publish(&self, events: Vec<EventEnvelope>) -> Result<(), String> {
+        let mut tx = self
+            .pool
+            .begin()
+            .await
+            .map_err(|e| format!("Database error: {}", e))?;
+        for event in &events {
+            let payload = serde_json::to_value(event)
+                .map_err(|e| format!("Failed to encode event {}: {}", event.event_id, e))?;
+            sqlx::query(
+                r#"
+                INSERT INTO event_outbox
+                (event_id, aggregate_id, sequence, event_type, tenant_id, occurred_at, payload)
+                VALUES ($1, $2, $3, $4, $5, $6, $7)
+                ON CONFLICT (event_id) DO NOTHING
+                "#,
+            )
+            .bind(event.event_id)
+            .bind(event.aggregate_id)
+            .bind(event.sequence as i64)
+            .bind(event.event_type())
+            .bind(event.tenant_id)
+            .bind(event.occurred_at)
+            .bind(&payload)
+            .execute(&mut *tx)
+            .await
+            .map_err(|e| {
+                error!("Failed to store event {} in outbox: {}", event.event_id, e);
+                format!("Database error: {}", e)
+            })?;
+        }
+        tx.commit()
+            .await
+            .map_err(|e| format!("Database error: {}", e))?;
+
+        self.pending.notify_one();
+        Ok(())
+    }
+
+    fn subscribe(&self) -> broadcast::Receiver<EventEnvelope> {
+        self.relay.subscribe()
+    }
+}
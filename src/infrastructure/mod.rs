@@ -7,11 +7,23 @@
 //! - External service integrations
 
 pub mod audit;
+pub mod call_debug;
+pub mod clock;
+pub mod idempotency;
 pub mod ivr;
+pub mod lnp;
+pub mod logging;
 pub mod media;
 pub mod messaging;
 pub mod persistence;
 pub mod protocols;
+pub mod replication;
+pub mod secrets;
+pub mod sequence_vault;
+pub mod setup_latency;
+pub mod storage;
+pub mod telemetry;
 pub mod tls;
+pub mod transcription;
 
 // Placeholder modules
@@ -0,0 +1,190 @@
+//! PostgreSQL implementation of AnnouncementRouteRepository
+
+use crate::domain::routing::{
+    AnnouncementRoute, AnnouncementRouteRepository, FollowUp, TimedMessage,
+};
+use async_trait::async_trait;
+use sqlx::types::Json;
+use sqlx::{FromRow, PgPool};
+use tracing::{debug, error};
+use uuid::Uuid;
+
+#[derive(FromRow)]
+struct AnnouncementRouteRow {
+    id: Uuid,
+    tenant: String,
+    number: String,
+    messages: Json<Vec<TimedMessage>>,
+    default_audio_file: String,
+    repeat_count: i32,
+    follow_up: Json<FollowUp>,
+    created_at: chrono::DateTime<chrono::Utc>,
+    updated_at: chrono::DateTime<chrono::Utc>,
+}
+
+impl From<AnnouncementRouteRow> for AnnouncementRoute {
+    fn from(r: AnnouncementRouteRow) -> Self {
+        AnnouncementRoute {
+            id: r.id,
+            tenant: r.tenant,
+            number: r.number,
+            messages: r.messages.0,
+            default_audio_file: r.default_audio_file,
+            repeat: r.repeat_count.max(1) as u32,
+            follow_up: r.follow_up.0,
+            created_at: r.created_at,
+            updated_at: r.updated_at,
+        }
+    }
+}
+
+pub struct PgAnnouncementRouteRepository {
+    pool: PgPool,
+}
+
+impl PgAnnouncementRouteRepository {
+    pub fn new(pool: PgPool) -> Self {
+        Self { pool }
+    }
+}
+
+#[async_trait]
+impl AnnouncementRouteRepository for PgAnnouncementRouteRepository {
+    async fn create(&self, route: &AnnouncementRoute) -> Result<(), String> {
+        debug!("Creating announcement route {}@{}", route.number, route.tenant);
+
+        sqlx::query(
+            r#"
+            INSERT INTO announcement_routes
+                (id, tenant, number, messages, default_audio_file, repeat_count, follow_up,
+                 created_at, updated_at)
+            VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9)
+            "#,
+        )
+        .bind(route.id)
+        .bind(&route.tenant)
+        .bind(&route.number)
+        .bind(Json(&route.messages))
+        .bind(&route.default_audio_file)
+        .bind(route.repeat as i32)
+        .bind(Json(&route.follow_up))
+        .bind(route.created_at)
+        .bind(route.updated_at)
+        .execute(&self.pool)
+        .await
+        .map_err(|e| {
+            error!("Failed to create announcement route: {}", e);
+            format!("Database error: {}", e)
+        })?;
+
+        Ok(())
+    }
+
+    async fn get_by_id(&self, id: Uuid) -> Result<Option<AnnouncementRoute>, String> {
+        sqlx::query_as::<_, AnnouncementRouteRow>(
+            r#"
+            SELECT id, tenant, number, messages, default_audio_file, repeat_count, follow_up,
+                   created_at, updated_at
+            FROM announcement_routes
+            WHERE id = $1
+            "#,
+        )
+        .bind(id)
+        .fetch_optional(&self.pool)
+        .await
+        .map(|row| row.map(Into::into))
+        .map_err(|e| {
+            error!("Failed to get announcement route: {}", e);
+            format!("Database error: {}", e)
+        })
+    }
+
+    async fn find_by_number(
+        &self,
+        tenant: &str,
+        number: &str,
+    ) -> Result<Option<AnnouncementRoute>, String> {
+        sqlx::query_as::<_, AnnouncementRouteRow>(
+            r#"
+            SELECT id, tenant, number, messages, default_audio_file, repeat_count, follow_up,
+                   created_at, updated_at
+            FROM announcement_routes
+            WHERE tenant = $1 AND number = $2
+            "#,
+        )
+        .bind(tenant)
+        .bind(number)
+        .fetch_optional(&self.pool)
+        .await
+        .map(|row| row.map(Into::into))
+        .map_err(|e| {
+            error!("Failed to find announcement route {}@{}: {}", number, tenant, e);
+            format!("Database error: {}", e)
+        })
+    }
+
+    async fn list(&self) -> Result<Vec<AnnouncementRoute>, String> {
+        let rows = sqlx::query_as::<_, AnnouncementRouteRow>(
+            r#"
+            SELECT id, tenant, number, messages, default_audio_file, repeat_count, follow_up,
+                   created_at, updated_at
+            FROM announcement_routes
+            ORDER BY tenant, number
+            "#,
+        )
+        .fetch_all(&self.pool)
+        .await
+        .map_err(|e| {
+            error!("Failed to list announcement routes: {}", e);
+            format!("Database error: {}", e)
+        })?;
+
+        Ok(rows.into_iter().map(Into::into).collect())
+    }
+
+    async fn update(&self, route: &AnnouncementRoute) -> Result<(), String> {
+        let result = sqlx::query(
+            r#"
+            UPDATE announcement_routes
+            SET tenant = $2, number = $3, messages = $4, default_audio_file = $5,
+                repeat_count = $6, follow_up = $7, updated_at = $8
+            WHERE id = $1
+            "#,
+        )
+        .bind(route.id)
+        .bind(&route.tenant)
+        .bind(&route.number)
+        .bind(Json(&route.messages))
+        .bind(&route.default_audio_file)
+        .bind(route.repeat as i32)
+        .bind(Json(&route.follow_up))
+        .bind(route.updated_at)
+        .execute(&self.pool)
+        .await
+        .map_err(|e| {
+            error!("Failed to update announcement route: {}", e);
+            format!("Database error: {}", e)
+        })?;
+
+        if result.rows_affected() == 0 {
+            return Err(format!("Announcement route not found: {}", route.id));
+        }
+        Ok(())
+    }
+
+    async fn delete(&self, id: Uuid) -> Result<(), String> {
+        let result = sqlx::query("DELETE FROM announcement_routes WHERE id = $1")
+            .bind(id)
+            .execute(&self.pool)
+            .await
+            .map_err(|e| {
+                error!("Failed to delete announcement route: {}", e);
+                format!("Database error: {}", e)
+            })?;
+
+        if result.rows_affected() == 0 {
+            return Err(format!("Announcement route not found: {}", id));
+        }
+        Ok(())
+    }
+}
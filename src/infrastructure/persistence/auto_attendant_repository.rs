@@ -0,0 +1,199 @@
+//! PostgreSQL implementation of AutoAttendantRepository
+
+use crate::domain::call_forwarding::TimeRange;
+use crate::domain::routing::{
+    AttendantNumber, AttendantOption, AutoAttendant, AutoAttendantRepository,
+};
+use crate::infrastructure::ivr::{DialByName, ExtensionCollection};
+use async_trait::async_trait;
+use sqlx::types::Json;
+use sqlx::{FromRow, PgPool};
+use tracing::{debug, error};
+use uuid::Uuid;
+
+#[derive(FromRow)]
+struct AutoAttendantRow {
+    id: Uuid,
+    tenant: String,
+    name: String,
+    numbers: Json<Vec<AttendantNumber>>,
+    day_greeting: String,
+    night_greeting: Option<String>,
+    business_hours: Json<Vec<TimeRange>>,
+    options: Json<Vec<AttendantOption>>,
+    extension_dialing: Option<Json<ExtensionCollection>>,
+    dial_by_name: Json<DialByName>,
+    max_retries: i32,
+    created_at: chrono::DateTime<chrono::Utc>,
+    updated_at: chrono::DateTime<chrono::Utc>,
+}
+
+impl From<AutoAttendantRow> for AutoAttendant {
+    fn from(r: AutoAttendantRow) -> Self {
+        AutoAttendant {
+            id: r.id,
+            tenant: r.tenant,
+            name: r.name,
+            numbers: r.numbers.0,
+            day_greeting: r.day_greeting,
+            night_greeting: r.night_greeting,
+            business_hours: r.business_hours.0,
+            options: r.options.0,
+            extension_dialing: r.extension_dialing.map(|e| e.0),
+            dial_by_name: r.dial_by_name.0,
+            max_retries: r.max_retries.max(1) as u32,
+            created_at: r.created_at,
+            updated_at: r.updated_at,
+        }
+    }
+}
+
+const COLUMNS: &str = "id, tenant, name, numbers, day_greeting, night_greeting, business_hours, \
+                       options, extension_dialing, dial_by_name, max_retries, created_at, updated_at";
+
+pub struct PgAutoAttendantRepository {
+    pool: PgPool,
+}
+
+impl PgAutoAttendantRepository {
+    pub fn new(pool: PgPool) -> Self {
+        Self { pool }
+    }
+}
+
+#[async_trait]
+impl AutoAttendantRepository for PgAutoAttendantRepository {
+    async fn create(&self, attendant: &AutoAttendant) -> Result<(), String> {
+        debug!("Creating auto-attendant {} for {}", attendant.name, attendant.tenant);
+
+        sqlx::query(
+            r#"
+            INSERT INTO auto_attendants
+                (id, tenant, name, numbers, day_greeting, night_greeting, business_hours,
+                 options, extension_dialing, dial_by_name, max_retries, created_at, updated_at)
+            VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12, $13)
+            "#,
+        )
+        .bind(attendant.id)
+        .bind(&attendant.tenant)
+        .bind(&attendant.name)
+        .bind(Json(&attendant.numbers))
+        .bind(&attendant.day_greeting)
+        .bind(&attendant.night_greeting)
+        .bind(Json(&attendant.business_hours))
+        .bind(Json(&attendant.options))
+        .bind(attendant.extension_dialing.as_ref().map(Json))
+        .bind(Json(&attendant.dial_by_name))
+        .bind(attendant.max_retries as i32)
+        .bind(attendant.created_at)
+        .bind(attendant.updated_at)
+        .execute(&self.pool)
+        .await
+        .map_err(|e| {
+            error!("Failed to create auto-attendant: {}", e);
+            format!("Database error: {}", e)
+        })?;
+
+        Ok(())
+    }
+
+    async fn get_by_id(&self, id: Uuid) -> Result<Option<AutoAttendant>, String> {
+        sqlx::query_as::<_, AutoAttendantRow>(&format!(
+            "SELECT {} FROM auto_attendants WHERE id = $1",
+            COLUMNS
+        ))
+        .bind(id)
+        .fetch_optional(&self.pool)
+        .await
+        .map(|row| row.map(Into::into))
+        .map_err(|e| {
+            error!("Failed to get auto-attendant: {}", e);
+            format!("Database error: {}", e)
+        })
+    }
+
+    async fn find_by_number(&self, tenant: &str, number: &str) -> Result<Vec<AutoAttendant>, String> {
+        let rows = sqlx::query_as::<_, AutoAttendantRow>(&format!(
+            "SELECT {} FROM auto_attendants \
+             WHERE tenant = $1 AND numbers @> jsonb_build_array(jsonb_build_object('number', $2::text)) \
+             ORDER BY created_at",
+            COLUMNS
+        ))
+        .bind(tenant)
+        .bind(number)
+        .fetch_all(&self.pool)
+        .await
+        .map_err(|e| {
+            error!("Failed to find auto-attendant for {}@{}: {}", number, tenant, e);
+            format!("Database error: {}", e)
+        })?;
+
+        Ok(rows.into_iter().map(Into::into).collect())
+    }
+
+    async fn list(&self) -> Result<Vec<AutoAttendant>, String> {
+        let rows = sqlx::query_as::<_, AutoAttendantRow>(&format!(
+            "SELECT {} FROM auto_attendants ORDER BY tenant, name",
+            COLUMNS
+        ))
+        .fetch_all(&self.pool)
+        .await
+        .map_err(|e| {
+            error!("Failed to list auto-attendants: {}", e);
+            format!("Database error: {}", e)
+        })?;
+
+        Ok(rows.into_iter().map(Into::into).collect())
+    }
+
+    async fn update(&self, attendant: &AutoAttendant) -> Result<(), String> {
+        let result = sqlx::query(
+            r#"
+            UPDATE auto_attendants
+            SET tenant = $2, name = $3, numbers = $4, day_greeting = $5, night_greeting = $6,
+                business_hours = $7, options = $8, extension_dialing = $9, dial_by_name = $10,
+                max_retries = $11, updated_at = $12
+            WHERE id = $1
+            "#,
+        )
+        .bind(attendant.id)
+        .bind(&attendant.tenant)
+        .bind(&attendant.name)
+        .bind(Json(&attendant.numbers))
+        .bind(&attendant.day_greeting)
+        .bind(&attendant.night_greeting)
+        .bind(Json(&attendant.business_hours))
+        .bind(Json(&attendant.options))
+        .bind(attendant.extension_dialing.as_ref().map(Json))
+        .bind(Json(&attendant.dial_by_name))
+        .bind(attendant.max_retries as i32)
+        .bind(attendant.updated_at)
+        .execute(&self.pool)
+        .await
+        .map_err(|e| {
+            error!("Failed to update auto-attendant: {}", e);
+            format!("Database error: {}", e)
+        })?;
+
+        if result.rows_affected() == 0 {
+            return Err(format!("Auto-attendant not found: {}", attendant.id));
+        }
+        Ok(())
+    }
+
+    async fn delete(&self, id: Uuid) -> Result<(), String> {
+        let result = sqlx::query("DELETE FROM auto_attendants WHERE id = $1")
+            .bind(id)
+            .execute(&self.pool)
+            .await
+            .map_err(|e| {
+                error!("Failed to delete auto-attendant: {}", e);
+                format!("Database error: {}", e)
+            })?;
+
+        if result.rows_affected() == 0 {
+            return Err(format!("Auto-attendant not found: {}", id));
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,267 @@
+//! Read-through cache of user lookups for degraded mode
+//!
+//! Every successful read is remembered; while a read fails with a transient
+//! database error, the last result of the same lookup is served instead, so
+//! the read-only API (users, their registrations) keeps answering through a
+//! failover. Writes go straight to the database and clear the cache.
+//!
+//! SIP digest auth keeps using the repository underneath: its own HA1 cache
+//! is opt-in.
+
+use super::health::{classify_db_error, DbErrorClass, DbHealth};
+use crate::domain::shared::error::Result;
+use crate::domain::shared::SortOrder;
+use crate::domain::user::{
+    ChangePassword, CreateUser, DirectoryQuery, UpdateUser, User, UserRepository,
+};
+use async_trait::async_trait;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use tracing::warn;
+
+/// Distinct lookups whose last result is kept
+pub const DEFAULT_USER_CACHE_CAPACITY: usize = 10_000;
+
+#[derive(Default)]
+struct Reads {
+    users: HashMap<String, Option<User>>,
+    lists: HashMap<String, Vec<User>>,
+    counts: HashMap<String, i64>,
+}
+
+/// User repository answering reads from cache while the database is down
+pub struct CachingUserRepository {
+    inner: Arc<dyn UserRepository>,
+    health: Arc<DbHealth>,
+    capacity: usize,
+    reads: Mutex<Reads>,
+}
+
+impl CachingUserRepository {
+    pub fn new(inner: Arc<dyn UserRepository>, health: Arc<DbHealth>) -> Self {
+        Self {
+            inner,
+            health,
+            capacity: DEFAULT_USER_CACHE_CAPACITY,
+            reads: Mutex::new(Reads::default()),
+        }
+    }
+
+    /// Remember a successful read, or answer a failed one from the last
+    /// result of the same lookup while the failure is transient
+    fn read_through<T: Clone>(
+        &self,
+        result: Result<T>,
+        key: String,
+        cache: impl Fn(&mut Reads) -> &mut HashMap<String, T>,
+    ) -> Result<T> {
+        let mut reads = self.reads.lock().unwrap();
+        let entries = cache(&mut reads);
+        match result {
+            Ok(value) => {
+                if !entries.contains_key(&key) && entries.len() >= self.capacity {
+                    entries.clear();
+                }
+                entries.insert(key, value.clone());
+                Ok(value)
+            }
+            Err(e) => {
+                let message = e.to_string();
+                if classify_db_error(&message) != DbErrorClass::Transient {
+                    return Err(e);
+                }
+                self.health.report_transient(&message);
+                match entries.get(&key) {
+                    Some(value) => {
+                        warn!("Database unavailable, serving cached {}", key);
+                        Ok(value.clone())
+                    }
+                    None => Err(e),
+                }
+            }
+        }
+    }
+
+    /// Drop every cached read after a successful write
+    fn written<T>(&self, result: Result<T>) -> Result<T> {
+        if result.is_ok() {
+            *self.reads.lock().unwrap() = Reads::default();
+        }
+        result
+    }
+}
+
+#[async_trait]
+impl UserRepository for CachingUserRepository {
+    async fn create(&self, data: CreateUser) -> Result<User> {
+        let result = self.inner.create(data).await;
+        self.written(result)
+    }
+
+    async fn find_by_id(&self, id: i32) -> Result<Option<User>> {
+        let result = self.inner.find_by_id(id).await;
+        self.read_through(result, format!("user {}", id), |reads| &mut reads.users)
+    }
+
+    async fn find_by_username(&self, username: &str) -> Result<Option<User>> {
+        let result = self.inner.find_by_username(username).await;
+        self.read_through(result, format!("user {}", username), |reads| {
+            &mut reads.users
+        })
+    }
+
+    async fn find_by_username_and_realm(
+        &self,
+        username: &str,
+        realm: &str,
+    ) -> Result<Option<User>> {
+        let result = self.inner.find_by_username_and_realm(username, realm).await;
+        self.read_through(result, format!("user {}@{}", username, realm), |reads| {
+            &mut reads.users
+        })
+    }
+
+    async fn list(&self, sort: &SortOrder, limit: i64, offset: i64) -> Result<Vec<User>> {
+        let result = self.inner.list(sort, limit, offset).await;
+        let key = format!("users {:?}/{}/{}", sort, limit, offset);
+        self.read_through(result, key, |reads| &mut reads.lists)
+    }
+
+    async fn list_by_realm(
+        &self,
+        realm: &str,
+        sort: &SortOrder,
+        limit: i64,
+        offset: i64,
+    ) -> Result<Vec<User>> {
+        let result = self.inner.list_by_realm(realm, sort, limit, offset).await;
+        let key = format!("users of {} {:?}/{}/{}", realm, sort, limit, offset);
+        self.read_through(result, key, |reads| &mut reads.lists)
+    }
+
+    async fn update(&self, id: i32, data: UpdateUser) -> Result<User> {
+        let result = self.inner.update(id, data).await;
+        self.written(result)
+    }
+
+    async fn change_password(&self, id: i32, data: ChangePassword) -> Result<()> {
+        let result = self.inner.change_password(id, data).await;
+        self.written(result)
+    }
+
+    async fn delete(&self, id: i32) -> Result<()> {
+        let result = self.inner.delete(id).await;
+        self.written(result)
+    }
+
+    async fn set_enabled(&self, id: i32, enabled: bool) -> Result<()> {
+        let result = self.inner.set_enabled(id, enabled).await;
+        self.written(result)
+    }
+
+    async fn count(&self) -> Result<i64> {
+        let result = self.inner.count().await;
+        self.read_through(result, "user count".to_string(), |reads| &mut reads.counts)
+    }
+
+    async fn count_by_realm(&self, realm: &str) -> Result<i64> {
+        let result = self.inner.count_by_realm(realm).await;
+        self.read_through(result, format!("user count of {}", realm), |reads| {
+            &mut reads.counts
+        })
+    }
+
+    async fn search(&self, query: &DirectoryQuery) -> Result<Vec<User>> {
+        let result = self.inner.search(query).await;
+        let key = format!("directory {:?}", query);
+        self.read_through(result, key, |reads| &mut reads.lists)
+    }
+
+    async fn verify_credentials(&self, username: &str, password: &str) -> Result<Option<User>> {
+        self.inner.verify_credentials(username, password).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::domain::shared::error::DomainError;
+    use crate::domain::user::repository::MockUserRepository;
+    use chrono::Utc;
+
+    fn user(id: i32, username: &str) -> User {
+        User {
+            id,
+            username: username.to_string(),
+            password_hash: "hash".to_string(),
+            sip_ha1: None,
+            realm: "localhost".to_string(),
+            display_name: None,
+            email: None,
+            department: None,
+            locale: None,
+            timezone: None,
+            enabled: true,
+            role_id: None,
+            created_at: Utc::now(),
+            updated_at: Utc::now(),
+        }
+    }
+
+    fn outage() -> DomainError {
+        DomainError::Internal("Database error: pool timed out".to_string())
+    }
+
+    #[tokio::test]
+    async fn test_reads_served_from_cache_during_outage() {
+        let mut mock = MockUserRepository::new();
+        let mut seq = mockall::Sequence::new();
+        mock.expect_find_by_id()
+            .times(1)
+            .in_sequence(&mut seq)
+            .returning(|id| Ok(Some(user(id, "alice"))));
+        mock.expect_find_by_id()
+            .times(2)
+            .in_sequence(&mut seq)
+            .returning(|_| Err(outage()));
+
+        let health = Arc::new(DbHealth::default());
+        let repo = CachingUserRepository::new(Arc::new(mock), health.clone());
+
+        assert!(repo.find_by_id(1).await.unwrap().is_some());
+        let cached = repo.find_by_id(1).await.unwrap().unwrap();
+        assert_eq!(cached.username, "alice");
+        assert!(health.is_degraded());
+
+        // Never looked up before: nothing to serve
+        assert!(repo.find_by_id(2).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_write_clears_cache_and_permanent_errors_pass_through() {
+        let mut mock = MockUserRepository::new();
+        let mut seq = mockall::Sequence::new();
+        mock.expect_count()
+            .times(1)
+            .in_sequence(&mut seq)
+            .returning(|| Ok(3));
+        mock.expect_set_enabled().times(1).returning(|_, _| Ok(()));
+        mock.expect_count()
+            .times(1)
+            .in_sequence(&mut seq)
+            .returning(|| Err(outage()));
+        mock.expect_find_by_username()
+            .returning(|_| Err(DomainError::Internal("syntax error at or near".to_string())));
+
+        let health = Arc::new(DbHealth::default());
+        let repo = CachingUserRepository::new(Arc::new(mock), health.clone());
+
+        assert_eq!(repo.count().await.unwrap(), 3);
+        repo.set_enabled(1, false).await.unwrap();
+        assert!(repo.count().await.is_err());
+
+        health.record_success();
+        assert!(repo.find_by_username("alice").await.is_err());
+        assert!(!health.is_degraded());
+    }
+}
@@ -1,8 +1,10 @@
 /// PostgreSQL implementation of CallQueueRepository
 use crate::domain::call_queue::{
-    AgentStatus, CallQueue, CallQueueRepository, OverflowAction, QueueMember, QueueStrategy,
+    AgentStatus, CallQueue, CallQueueRepository, InactivityAction, OverflowAction, OverflowRule,
+    QueueMember, QueueStrategy,
 };
 use async_trait::async_trait;
+use sqlx::postgres::PgRow;
 use sqlx::{PgPool, Row};
 use std::time::Duration;
 use tracing::{debug, error};
@@ -18,6 +20,19 @@ impl PgCallQueueRepository {
     }
 }
 
+/// Overflow rules of a queue, stored as JSON
+fn overflow_rules_json(queue: &CallQueue) -> serde_json::Value {
+    serde_json::to_value(&queue.overflow_rules).unwrap_or_else(|_| serde_json::json!([]))
+}
+
+fn overflow_rules_from(row: &PgRow) -> Vec<OverflowRule> {
+    let value: serde_json::Value = row.get("overflow_rules");
+    serde_json::from_value(value).unwrap_or_else(|e| {
+        error!("Invalid overflow rules of call queue: {}", e);
+        Vec::new()
+    })
+}
+
 #[async_trait]
 impl CallQueueRepository for PgCallQueueRepository {
     async fn create_queue(&self, queue: CallQueue) -> Result<CallQueue, String> {
@@ -30,8 +45,11 @@ impl CallQueueRepository for PgCallQueueRepository {
             (id, name, extension, strategy, max_wait_time_secs, max_queue_size, ring_timeout_secs,
              retry_delay_secs, max_retries, wrap_up_time_secs, announce_position, announce_wait_time,
              music_on_hold, periodic_announce, periodic_announce_frequency_secs,
-             overflow_queue_id, overflow_action, created_at, updated_at)
-            VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12, $13, $14, $15, $16, $17, $18, $19)
+             overflow_queue_id, overflow_action, service_level_threshold_secs, callback_digit,
+             callback_max_attempts, callback_retry_delay_secs, max_missed_offers, inactivity_action,
+             overflow_rules,
+             created_at, updated_at)
+            VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12, $13, $14, $15, $16, $17, $18, $19, $20, $21, $22, $23, $24, $25, $26)
             "#,
         )
         .bind(queue.id)
@@ -51,6 +69,13 @@ impl CallQueueRepository for PgCallQueueRepository {
         .bind(queue.periodic_announce_frequency.as_secs() as i64)
         .bind(queue.overflow_queue_id)
         .bind(&overflow_action_str)
+        .bind(queue.service_level_threshold.as_secs() as i64)
+        .bind(queue.callback_digit.map(|digit| digit.to_string()))
+        .bind(queue.callback_max_attempts as i32)
+        .bind(queue.callback_retry_delay.as_secs() as i64)
+        .bind(queue.max_missed_offers as i32)
+        .bind(format!("{:?}", queue.inactivity_action))
+        .bind(overflow_rules_json(&queue))
         .bind(queue.created_at)
         .bind(queue.updated_at)
         .execute(&self.pool)
@@ -74,7 +99,10 @@ impl CallQueueRepository for PgCallQueueRepository {
             SELECT id, name, extension, strategy, max_wait_time_secs, max_queue_size, ring_timeout_secs,
                    retry_delay_secs, max_retries, wrap_up_time_secs, announce_position, announce_wait_time,
                    music_on_hold, periodic_announce, periodic_announce_frequency_secs,
-                   overflow_queue_id, overflow_action, created_at, updated_at
+                   overflow_queue_id, overflow_action, service_level_threshold_secs, callback_digit,
+                   callback_max_attempts, callback_retry_delay_secs, max_missed_offers, inactivity_action,
+                   overflow_rules,
+                   created_at, updated_at
             FROM call_queues
             WHERE id = $1
             "#,
@@ -127,6 +155,18 @@ impl CallQueueRepository for PgCallQueueRepository {
                     ),
                     overflow_queue_id: row.get("overflow_queue_id"),
                     overflow_action,
+                    service_level_threshold: Duration::from_secs(row.get::<i64, _>("service_level_threshold_secs") as u64),
+                    callback_digit: row
+                        .get::<Option<String>, _>("callback_digit")
+                        .and_then(|digit| digit.chars().next()),
+                    callback_max_attempts: row.get::<i32, _>("callback_max_attempts") as u32,
+                    callback_retry_delay: Duration::from_secs(row.get::<i64, _>("callback_retry_delay_secs") as u64),
+                    max_missed_offers: row.get::<i32, _>("max_missed_offers") as u32,
+                    inactivity_action: match row.get::<String, _>("inactivity_action").as_str() {
+                        "LogOut" => InactivityAction::LogOut,
+                        _ => InactivityAction::Pause,
+                    },
+                    overflow_rules: overflow_rules_from(&row),
                     created_at: row.get("created_at"),
                     updated_at: row.get("updated_at"),
                 };
@@ -147,7 +187,10 @@ impl CallQueueRepository for PgCallQueueRepository {
             SELECT id, name, extension, strategy, max_wait_time_secs, max_queue_size, ring_timeout_secs,
                    retry_delay_secs, max_retries, wrap_up_time_secs, announce_position, announce_wait_time,
                    music_on_hold, periodic_announce, periodic_announce_frequency_secs,
-                   overflow_queue_id, overflow_action, created_at, updated_at
+                   overflow_queue_id, overflow_action, service_level_threshold_secs, callback_digit,
+                   callback_max_attempts, callback_retry_delay_secs, max_missed_offers, inactivity_action,
+                   overflow_rules,
+                   created_at, updated_at
             FROM call_queues
             WHERE extension = $1
             "#,
@@ -200,6 +243,18 @@ impl CallQueueRepository for PgCallQueueRepository {
                     ),
                     overflow_queue_id: row.get("overflow_queue_id"),
                     overflow_action,
+                    service_level_threshold: Duration::from_secs(row.get::<i64, _>("service_level_threshold_secs") as u64),
+                    callback_digit: row
+                        .get::<Option<String>, _>("callback_digit")
+                        .and_then(|digit| digit.chars().next()),
+                    callback_max_attempts: row.get::<i32, _>("callback_max_attempts") as u32,
+                    callback_retry_delay: Duration::from_secs(row.get::<i64, _>("callback_retry_delay_secs") as u64),
+                    max_missed_offers: row.get::<i32, _>("max_missed_offers") as u32,
+                    inactivity_action: match row.get::<String, _>("inactivity_action").as_str() {
+                        "LogOut" => InactivityAction::LogOut,
+                        _ => InactivityAction::Pause,
+                    },
+                    overflow_rules: overflow_rules_from(&row),
                     created_at: row.get("created_at"),
                     updated_at: row.get("updated_at"),
                 };
@@ -226,7 +281,10 @@ impl CallQueueRepository for PgCallQueueRepository {
                 max_retries = $9, wrap_up_time_secs = $10, announce_position = $11,
                 announce_wait_time = $12, music_on_hold = $13, periodic_announce = $14,
                 periodic_announce_frequency_secs = $15, overflow_queue_id = $16,
-                overflow_action = $17, updated_at = $18
+                overflow_action = $17, service_level_threshold_secs = $18, callback_digit = $19,
+                callback_max_attempts = $20, callback_retry_delay_secs = $21,
+                max_missed_offers = $22, inactivity_action = $23, updated_at = $24,
+                overflow_rules = $25
             WHERE id = $1
             "#,
         )
@@ -247,7 +305,14 @@ impl CallQueueRepository for PgCallQueueRepository {
         .bind(queue.periodic_announce_frequency.as_secs() as i64)
         .bind(queue.overflow_queue_id)
         .bind(&overflow_action_str)
+        .bind(queue.service_level_threshold.as_secs() as i64)
+        .bind(queue.callback_digit.map(|digit| digit.to_string()))
+        .bind(queue.callback_max_attempts as i32)
+        .bind(queue.callback_retry_delay.as_secs() as i64)
+        .bind(queue.max_missed_offers as i32)
+        .bind(format!("{:?}", queue.inactivity_action))
         .bind(queue.updated_at)
+        .bind(overflow_rules_json(queue))
         .execute(&self.pool)
         .await;
 
@@ -287,7 +352,10 @@ impl CallQueueRepository for PgCallQueueRepository {
             SELECT id, name, extension, strategy, max_wait_time_secs, max_queue_size, ring_timeout_secs,
                    retry_delay_secs, max_retries, wrap_up_time_secs, announce_position, announce_wait_time,
                    music_on_hold, periodic_announce, periodic_announce_frequency_secs,
-                   overflow_queue_id, overflow_action, created_at, updated_at
+                   overflow_queue_id, overflow_action, service_level_threshold_secs, callback_digit,
+                   callback_max_attempts, callback_retry_delay_secs, max_missed_offers, inactivity_action,
+                   overflow_rules,
+                   created_at, updated_at
             FROM call_queues
             ORDER BY name
             "#,
@@ -342,6 +410,18 @@ impl CallQueueRepository for PgCallQueueRepository {
                             ),
                             overflow_queue_id: row.get("overflow_queue_id"),
                             overflow_action,
+                            service_level_threshold: Duration::from_secs(row.get::<i64, _>("service_level_threshold_secs") as u64),
+                            callback_digit: row
+                                .get::<Option<String>, _>("callback_digit")
+                                .and_then(|digit| digit.chars().next()),
+                            callback_max_attempts: row.get::<i32, _>("callback_max_attempts") as u32,
+                            callback_retry_delay: Duration::from_secs(row.get::<i64, _>("callback_retry_delay_secs") as u64),
+                            max_missed_offers: row.get::<i32, _>("max_missed_offers") as u32,
+                            inactivity_action: match row.get::<String, _>("inactivity_action").as_str() {
+                                "LogOut" => InactivityAction::LogOut,
+                                _ => InactivityAction::Pause,
+                            },
+                            overflow_rules: overflow_rules_from(row),
                             created_at: row.get("created_at"),
                             updated_at: row.get("updated_at"),
                         }
@@ -364,8 +444,9 @@ impl CallQueueRepository for PgCallQueueRepository {
             r#"
             INSERT INTO queue_members
             (id, queue_id, user_id, username, extension, status, penalty, paused, paused_reason,
-             last_call_time, total_calls, answered_calls, missed_calls, total_talk_time_secs, joined_at)
-            VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12, $13, $14, $15)
+             last_call_time, total_calls, answered_calls, missed_calls, total_talk_time_secs, joined_at,
+             dnd_linked)
+            VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12, $13, $14, $15, $16)
             "#,
         )
         .bind(member.id)
@@ -383,6 +464,7 @@ impl CallQueueRepository for PgCallQueueRepository {
         .bind(member.missed_calls as i64)
         .bind(member.total_talk_time.as_secs() as i64)
         .bind(member.joined_at)
+        .bind(member.dnd_linked)
         .execute(&self.pool)
         .await;
 
@@ -425,7 +507,7 @@ impl CallQueueRepository for PgCallQueueRepository {
             SET username = $2, extension = $3, status = $4, penalty = $5,
                 paused = $6, paused_reason = $7, last_call_time = $8,
                 total_calls = $9, answered_calls = $10, missed_calls = $11,
-                total_talk_time_secs = $12
+                total_talk_time_secs = $12, dnd_linked = $13
             WHERE id = $1
             "#,
         )
@@ -441,6 +523,7 @@ impl CallQueueRepository for PgCallQueueRepository {
         .bind(member.answered_calls as i64)
         .bind(member.missed_calls as i64)
         .bind(member.total_talk_time.as_secs() as i64)
+        .bind(member.dnd_linked)
         .execute(&self.pool)
         .await;
 
@@ -460,7 +543,8 @@ impl CallQueueRepository for PgCallQueueRepository {
         let result = sqlx::query(
             r#"
             SELECT id, queue_id, user_id, username, extension, status, penalty, paused, paused_reason,
-                   last_call_time, total_calls, answered_calls, missed_calls, total_talk_time_secs, joined_at
+                   last_call_time, total_calls, answered_calls, missed_calls, total_talk_time_secs, joined_at,
+                   dnd_linked
             FROM queue_members
             WHERE queue_id = $1
             ORDER BY joined_at
@@ -502,6 +586,8 @@ impl CallQueueRepository for PgCallQueueRepository {
                                 row.get::<i64, _>("total_talk_time_secs") as u64,
                             ),
                             joined_at: row.get("joined_at"),
+                            consecutive_missed: 0,
+                            dnd_linked: row.get("dnd_linked"),
                         }
                     })
                     .collect();
@@ -519,7 +605,8 @@ impl CallQueueRepository for PgCallQueueRepository {
         let result = sqlx::query(
             r#"
             SELECT id, queue_id, user_id, username, extension, status, penalty, paused, paused_reason,
-                   last_call_time, total_calls, answered_calls, missed_calls, total_talk_time_secs, joined_at
+                   last_call_time, total_calls, answered_calls, missed_calls, total_talk_time_secs, joined_at,
+                   dnd_linked
             FROM queue_members
             WHERE id = $1
             "#,
@@ -557,6 +644,8 @@ impl CallQueueRepository for PgCallQueueRepository {
                         row.get::<i64, _>("total_talk_time_secs") as u64,
                     ),
                     joined_at: row.get("joined_at"),
+                    consecutive_missed: 0,
+                    dnd_linked: row.get("dnd_linked"),
                 };
 
                 Ok(Some(member))
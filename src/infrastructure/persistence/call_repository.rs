@@ -0,0 +1,159 @@
+//! PostgreSQL implementation of CallRepository
+//!
+//! The aggregate is stored whole as JSON; its state and participants are
+//! copied into columns for the lookups. Saves compare the version column.
+
+use crate::domain::call::{Call, CallRepository, CallState};
+use crate::domain::shared::error::{DomainError, Result};
+use crate::domain::shared::value_objects::{CallId, EndpointId, SipUri};
+use async_trait::async_trait;
+use sqlx::types::Json;
+use sqlx::{FromRow, PgPool};
+use tracing::{debug, error};
+
+#[derive(FromRow)]
+struct CallRow {
+    aggregate: Json<Call>,
+    version: i64,
+}
+
+impl From<CallRow> for Call {
+    fn from(row: CallRow) -> Self {
+        let mut call = row.aggregate.0;
+        call.set_version(row.version as u64);
+        call
+    }
+}
+
+fn state_name(state: &CallState) -> &'static str {
+    match state {
+        CallState::Initiating => "initiating",
+        CallState::Ringing => "ringing",
+        CallState::Answered => "answered",
+        CallState::OnHold => "on_hold",
+        CallState::Transferring => "transferring",
+        CallState::Ended(_) => "ended",
+    }
+}
+
+fn database_error(action: &str, e: sqlx::Error) -> DomainError {
+    error!("Failed to {}: {}", action, e);
+    DomainError::Internal(format!("Failed to {}: {}", action, e))
+}
+
+pub struct PgCallRepository {
+    pool: PgPool,
+}
+
+impl PgCallRepository {
+    pub fn new(pool: PgPool) -> Self {
+        Self { pool }
+    }
+}
+
+#[async_trait]
+impl CallRepository for PgCallRepository {
+    async fn find_by_id(&self, id: &CallId) -> Result<Option<Call>> {
+        sqlx::query_as::<_, CallRow>("SELECT aggregate, version FROM calls WHERE id = $1")
+            .bind(id.as_uuid())
+            .fetch_optional(&self.pool)
+            .await
+            .map(|row| row.map(Into::into))
+            .map_err(|e| database_error("find call", e))
+    }
+
+    async fn save(&self, call: &mut Call) -> Result<()> {
+        let expected = call.version() as i64;
+        debug!("Saving call {} over version {}", call.id(), expected);
+
+        // A new call is inserted; a loaded one is updated only at the
+        // version it was loaded at
+        let sql = if expected == 0 {
+            r#"
+            INSERT INTO calls
+                (id, state, active, caller_aor, callee_aor, caller_endpoint, callee_endpoint,
+                 aggregate, version, started_at, updated_at)
+            VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9 + 1, $10, NOW())
+            ON CONFLICT (id) DO NOTHING
+            "#
+        } else {
+            r#"
+            UPDATE calls SET
+                state = $2, active = $3, caller_aor = $4, callee_aor = $5,
+                caller_endpoint = $6, callee_endpoint = $7, aggregate = $8,
+                version = $9 + 1, started_at = $10, updated_at = NOW()
+            WHERE id = $1 AND version = $9
+            "#
+        };
+        let result = sqlx::query(sql)
+            .bind(call.id().as_uuid())
+            .bind(state_name(call.state()))
+            .bind(call.is_active())
+            .bind(call.caller().uri().address_of_record().to_string())
+            .bind(call.callee().uri().address_of_record().to_string())
+            .bind(call.caller().endpoint_id().as_uuid())
+            .bind(call.callee().endpoint_id().as_uuid())
+            .bind(Json(&*call))
+            .bind(expected)
+            .bind(*call.started_at())
+            .execute(&self.pool)
+            .await
+            .map_err(|e| database_error("save call", e))?;
+
+        if result.rows_affected() == 0 {
+            return Err(DomainError::Conflict(format!(
+                "Call {} changed since version {}",
+                call.id(),
+                expected
+            )));
+        }
+        call.set_version(expected as u64 + 1);
+        Ok(())
+    }
+
+    async fn delete(&self, id: &CallId) -> Result<()> {
+        let result = sqlx::query("DELETE FROM calls WHERE id = $1")
+            .bind(id.as_uuid())
+            .execute(&self.pool)
+            .await
+            .map_err(|e| database_error("delete call", e))?;
+        if result.rows_affected() == 0 {
+            return Err(DomainError::NotFound(format!("Call {}", id)));
+        }
+        Ok(())
+    }
+
+    async fn find_active_calls(&self) -> Result<Vec<Call>> {
+        let rows = sqlx::query_as::<_, CallRow>(
+            "SELECT aggregate, version FROM calls WHERE active ORDER BY started_at",
+        )
+        .fetch_all(&self.pool)
+        .await
+        .map_err(|e| database_error("find active calls", e))?;
+        Ok(rows.into_iter().map(Into::into).collect())
+    }
+
+    async fn find_by_endpoint(&self, endpoint_id: &EndpointId) -> Result<Vec<Call>> {
+        let rows = sqlx::query_as::<_, CallRow>(
+            "SELECT aggregate, version FROM calls \
+             WHERE caller_endpoint = $1 OR callee_endpoint = $1 ORDER BY started_at",
+        )
+        .bind(endpoint_id.as_uuid())
+        .fetch_all(&self.pool)
+        .await
+        .map_err(|e| database_error("find calls of endpoint", e))?;
+        Ok(rows.into_iter().map(Into::into).collect())
+    }
+
+    async fn find_active_by_participant(&self, uri: &SipUri) -> Result<Vec<Call>> {
+        let rows = sqlx::query_as::<_, CallRow>(
+            "SELECT aggregate, version FROM calls \
+             WHERE active AND (caller_aor = $1 OR callee_aor = $1) ORDER BY started_at",
+        )
+        .bind(uri.address_of_record().to_string())
+        .fetch_all(&self.pool)
+        .await
+        .map_err(|e| database_error("find calls of participant", e))?;
+        Ok(rows.into_iter().map(Into::into).collect())
+    }
+}
@@ -0,0 +1,324 @@
+//! Append-only journal of CDR writes waiting for the database
+//!
+//! While the database is degraded, CDR creates and updates are appended to a
+//! JSON-lines file as they are queued, and a completion line is appended as
+//! each one reaches the database. Replaying the file on startup restores the
+//! writes a restart would otherwise lose. The file is compacted on open and
+//! emptied whenever the backlog drains.
+
+use crate::domain::cdr::CallDetailRecord;
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+use std::fs::{File, OpenOptions};
+use std::io::{BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+use tracing::{info, warn};
+use uuid::Uuid;
+
+/// Whether a queued write inserts or updates its record
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum WriteKind {
+    Create,
+    Update,
+}
+
+/// A CDR write waiting for the database
+#[derive(Debug, Clone)]
+pub struct PendingWrite {
+    pub kind: WriteKind,
+    pub cdr: CallDetailRecord,
+    /// Bumped whenever a later write of the same record is folded in
+    revision: u64,
+}
+
+/// One line of the journal file
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(tag = "op", rename_all = "lowercase")]
+enum JournalEntry {
+    Write {
+        kind: WriteKind,
+        cdr: Box<CallDetailRecord>,
+    },
+    /// The record's write reached the database or was given up on
+    Done { id: Uuid },
+}
+
+/// Pending CDR writes in order, mirrored to a file when one is configured
+pub struct CdrJournal {
+    pending: VecDeque<PendingWrite>,
+    path: Option<PathBuf>,
+    file: Option<File>,
+    next_revision: u64,
+}
+
+impl CdrJournal {
+    /// Journal kept in memory only, lost on restart
+    pub fn in_memory() -> Self {
+        Self {
+            pending: VecDeque::new(),
+            path: None,
+            file: None,
+            next_revision: 0,
+        }
+    }
+
+    /// Journal backed by `path`, replaying the writes it still holds
+    pub fn open(path: impl AsRef<Path>) -> std::io::Result<Self> {
+        let path = path.as_ref().to_path_buf();
+        let mut journal = Self::in_memory();
+
+        if path.exists() {
+            let reader = BufReader::new(File::open(&path)?);
+            for (number, line) in reader.lines().enumerate() {
+                let line = line?;
+                if line.trim().is_empty() {
+                    continue;
+                }
+                match serde_json::from_str(&line) {
+                    Ok(JournalEntry::Write { kind, cdr }) => journal.fold(kind, *cdr),
+                    Ok(JournalEntry::Done { id }) => {
+                        journal.pending.retain(|w| w.cdr.id != id);
+                    }
+                    // A torn last line from a crash mid-append
+                    Err(e) => warn!("Skipping CDR journal line {} of {:?}: {}", number + 1, path, e),
+                }
+            }
+            if !journal.pending.is_empty() {
+                info!(
+                    "Recovered {} queued CDR writes from {:?}",
+                    journal.pending.len(),
+                    path
+                );
+            }
+        } else if let Some(dir) = path.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+            std::fs::create_dir_all(dir)?;
+        }
+
+        journal.path = Some(path);
+        journal.compact()?;
+        Ok(journal)
+    }
+
+    /// Number of writes waiting
+    pub fn len(&self) -> usize {
+        self.pending.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.pending.is_empty()
+    }
+
+    /// Oldest waiting write
+    pub fn front(&self) -> Option<PendingWrite> {
+        self.pending.front().cloned()
+    }
+
+    /// Latest queued version of the first record matching `pred`
+    pub fn find<F>(&self, pred: F) -> Option<CallDetailRecord>
+    where
+        F: Fn(&CallDetailRecord) -> bool,
+    {
+        self.pending.iter().find(|w| pred(&w.cdr)).map(|w| w.cdr.clone())
+    }
+
+    /// Queue a write, keeping at most `capacity` records
+    ///
+    /// Repeated writes of the same record collapse into one; a queued create
+    /// stays a create so the row is still inserted. Returns the id of the
+    /// oldest record if it had to be dropped to make room.
+    pub fn push(&mut self, kind: WriteKind, cdr: &CallDetailRecord, capacity: usize) -> Option<Uuid> {
+        let mut dropped = None;
+        if !self.pending.iter().any(|w| w.cdr.id == cdr.id) && self.pending.len() >= capacity {
+            if let Some(oldest) = self.pending.pop_front() {
+                self.append(&JournalEntry::Done { id: oldest.cdr.id });
+                dropped = Some(oldest.cdr.id);
+            }
+        }
+
+        self.fold(kind, cdr.clone());
+        self.append(&JournalEntry::Write {
+            kind,
+            cdr: Box::new(cdr.clone()),
+        });
+        dropped
+    }
+
+    /// Mark `write` (as returned by [`front`](Self::front)) done
+    ///
+    /// If a later write of the record was folded in meanwhile, that one stays
+    /// queued, as an update since the row now exists.
+    pub fn complete(&mut self, write: &PendingWrite) {
+        let Some(index) = self.pending.iter().position(|w| w.cdr.id == write.cdr.id) else {
+            return;
+        };
+        self.append(&JournalEntry::Done { id: write.cdr.id });
+
+        if self.pending[index].revision == write.revision {
+            self.pending.remove(index);
+        } else {
+            let newer = &mut self.pending[index];
+            newer.kind = WriteKind::Update;
+            let entry = JournalEntry::Write {
+                kind: WriteKind::Update,
+                cdr: Box::new(newer.cdr.clone()),
+            };
+            self.append(&entry);
+        }
+
+        if self.pending.is_empty() {
+            if let Err(e) = self.compact() {
+                warn!("Failed to truncate the CDR journal: {}", e);
+            }
+        }
+    }
+
+    fn fold(&mut self, kind: WriteKind, cdr: CallDetailRecord) {
+        self.next_revision += 1;
+        let revision = self.next_revision;
+        match self.pending.iter_mut().find(|w| w.cdr.id == cdr.id) {
+            Some(existing) => {
+                existing.cdr = cdr;
+                existing.revision = revision;
+            }
+            None => self.pending.push_back(PendingWrite { kind, cdr, revision }),
+        }
+    }
+
+    fn append(&mut self, entry: &JournalEntry) {
+        let Some(file) = self.file.as_mut() else {
+            return;
+        };
+        let mut line = match serde_json::to_vec(entry) {
+            Ok(line) => line,
+            Err(e) => {
+                warn!("Failed to encode CDR journal entry: {}", e);
+                return;
+            }
+        };
+        line.push(b'\n');
+        if let Err(e) = file.write_all(&line).and_then(|_| file.sync_data()) {
+            warn!("Failed to append to the CDR journal: {}", e);
+        }
+    }
+
+    /// Rewrite the file with only the writes still pending
+    fn compact(&mut self) -> std::io::Result<()> {
+        let Some(path) = self.path.clone() else {
+            return Ok(());
+        };
+        self.file = None;
+
+        let staging = path.with_extension("tmp");
+        {
+            let mut file = File::create(&staging)?;
+            for write in &self.pending {
+                let entry = JournalEntry::Write {
+                    kind: write.kind,
+                    cdr: Box::new(write.cdr.clone()),
+                };
+                serde_json::to_writer(&mut file, &entry)?;
+                file.write_all(b"\n")?;
+            }
+            file.sync_all()?;
+        }
+        std::fs::rename(&staging, &path)?;
+
+        self.file = Some(OpenOptions::new().append(true).open(&path)?);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::domain::cdr::{CallDirection, CallStatus};
+
+    fn cdr(call_id: &str) -> CallDetailRecord {
+        CallDetailRecord::new(
+            call_id.to_string(),
+            "alice".to_string(),
+            "sip:alice@localhost".to_string(),
+            "192.168.1.100".to_string(),
+            "bob".to_string(),
+            "sip:bob@localhost".to_string(),
+            CallDirection::Internal,
+        )
+    }
+
+    #[test]
+    fn test_pending_writes_survive_reopen() {
+        let dir = journal_dir("reopen");
+        let path = dir.join("cdr_journal.jsonl");
+
+        let mut first = cdr("call-1");
+        let second = cdr("call-2");
+        {
+            let mut journal = CdrJournal::open(&path).unwrap();
+            journal.push(WriteKind::Create, &first, 10);
+            journal.push(WriteKind::Create, &second, 10);
+            first.mark_ended(CallStatus::Completed, None, Some(200));
+            journal.push(WriteKind::Update, &first, 10);
+
+            let applied = journal.front().unwrap();
+            assert_eq!(applied.cdr.call_id, "call-1");
+            journal.complete(&applied);
+        }
+
+        let journal = CdrJournal::open(&path).unwrap();
+        assert_eq!(journal.len(), 1);
+        let replayed = journal.front().unwrap();
+        assert_eq!(replayed.cdr.call_id, "call-2");
+        assert_eq!(replayed.kind, WriteKind::Create);
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_write_folded_in_during_flush_stays_queued_as_update() {
+        let dir = journal_dir("fold");
+        let path = dir.join("cdr_journal.jsonl");
+
+        let mut record = cdr("call-1");
+        {
+            let mut journal = CdrJournal::open(&path).unwrap();
+            journal.push(WriteKind::Create, &record, 10);
+            let in_flight = journal.front().unwrap();
+
+            record.mark_ended(CallStatus::Completed, None, Some(200));
+            journal.push(WriteKind::Update, &record, 10);
+            journal.complete(&in_flight);
+            assert_eq!(journal.front().unwrap().kind, WriteKind::Update);
+        }
+
+        let journal = CdrJournal::open(&path).unwrap();
+        let replayed = journal.front().unwrap();
+        assert_eq!(replayed.kind, WriteKind::Update);
+        assert_eq!(replayed.cdr.status, CallStatus::Completed);
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_torn_last_line_is_skipped() {
+        let dir = journal_dir("torn");
+        let path = dir.join("cdr_journal.jsonl");
+        {
+            let mut journal = CdrJournal::open(&path).unwrap();
+            journal.push(WriteKind::Create, &cdr("call-1"), 10);
+        }
+        let mut file = OpenOptions::new().append(true).open(&path).unwrap();
+        file.write_all(b"{\"op\":\"write\",\"kind\":\"cre").unwrap();
+
+        let journal = CdrJournal::open(&path).unwrap();
+        assert_eq!(journal.len(), 1);
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    fn journal_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("yakyak-cdr-journal-{}-{}", name, Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+}
@@ -1,11 +1,31 @@
 //! PostgreSQL implementation of CDR Repository
 
 use crate::domain::cdr::{CallDetailRecord, CallDirection, CallStatus, CdrFilters, CdrRepository};
+use crate::domain::cdr_retention::{CdrRetentionRepository, RetentionCandidate};
+use crate::domain::privacy::{
+    ErasureSubject, Pseudonymizer, SubjectBearing, SubjectReference, SubjectStore,
+};
+use crate::domain::shared::SortOrder;
 use async_trait::async_trait;
+use chrono::{DateTime, NaiveDate, Utc};
 use sqlx::{FromRow, PgPool};
 use tracing::{debug, error};
 use uuid::Uuid;
 
+/// ORDER BY clause for a CDR listing; ties are broken by ID so pages are stable
+fn order_by(sort: Option<&SortOrder>) -> String {
+    let Some(sort) = sort else {
+        return "start_time DESC, id DESC".to_string();
+    };
+    let column = match sort.field.as_str() {
+        "call_duration" => "call_duration",
+        "caller_username" => "caller_username",
+        "callee_username" => "callee_username",
+        _ => "start_time",
+    };
+    format!("{} {}, id {}", column, sort.direction(), sort.direction())
+}
+
 #[derive(FromRow)]
 struct CdrRow {
     id: Uuid,
@@ -31,6 +51,26 @@ struct CdrRow {
     rtp_packets_received: Option<i64>,
     rtp_bytes_sent: Option<i64>,
     rtp_bytes_received: Option<i64>,
+    correlation_id: Option<String>,
+    dialed_number: Option<String>,
+    redirect_count: i32,
+    hold_duration: i32,
+    hold_count: i32,
+    test_call: bool,
+    announcement_variant: Option<String>,
+    early_media_time: Option<chrono::DateTime<chrono::Utc>>,
+    ack_time: Option<chrono::DateTime<chrono::Utc>>,
+    trunk: Option<String>,
+    suspect_answer: bool,
+    live_transcribed: bool,
+    codec_fallback: Option<String>,
+    ring_time: Option<chrono::DateTime<chrono::Utc>>,
+    ring_duration: Option<i32>,
+    talk_duration: Option<i32>,
+    auth_latency_ms: Option<i32>,
+    routing_latency_ms: Option<i32>,
+    provisional_latency_ms: Option<i32>,
+    answer_latency_ms: Option<i32>,
     created_at: chrono::DateTime<chrono::Utc>,
     updated_at: chrono::DateTime<chrono::Utc>,
 }
@@ -65,12 +105,51 @@ impl From<CdrRow> for CallDetailRecord {
             rtp_packets_received: r.rtp_packets_received,
             rtp_bytes_sent: r.rtp_bytes_sent,
             rtp_bytes_received: r.rtp_bytes_received,
+            correlation_id: r.correlation_id,
+            dialed_number: r.dialed_number,
+            redirect_count: r.redirect_count,
+            hold_duration: r.hold_duration,
+            hold_count: r.hold_count,
+            test_call: r.test_call,
+            announcement_variant: r.announcement_variant,
+            early_media_time: r.early_media_time,
+            ack_time: r.ack_time,
+            trunk: r.trunk,
+            suspect_answer: r.suspect_answer,
+            live_transcribed: r.live_transcribed,
+            codec_fallback: r.codec_fallback,
+            ring_time: r.ring_time,
+            ring_duration: r.ring_duration,
+            talk_duration: r.talk_duration,
+            auth_latency_ms: r.auth_latency_ms,
+            routing_latency_ms: r.routing_latency_ms,
+            provisional_latency_ms: r.provisional_latency_ms,
+            answer_latency_ms: r.answer_latency_ms,
             created_at: r.created_at,
             updated_at: r.updated_at,
         }
     }
 }
 
+/// Columns of a [`CdrRow`]
+const CDR_COLUMNS: &str = "id, call_id, \
+    caller_username, caller_uri, caller_ip, callee_username, callee_uri, callee_ip, \
+    direction, start_time, answer_time, end_time, \
+    setup_duration, call_duration, total_duration, status, end_reason, sip_response_code, \
+    codec, rtp_packets_sent, rtp_packets_received, rtp_bytes_sent, rtp_bytes_received, \
+    correlation_id, dialed_number, redirect_count, hold_duration, hold_count, test_call, \
+    announcement_variant, early_media_time, ack_time, trunk, suspect_answer, live_transcribed, \
+    codec_fallback, ring_time, ring_duration, talk_duration, \
+    auth_latency_ms, routing_latency_ms, provisional_latency_ms, answer_latency_ms, \
+    created_at, updated_at";
+
+#[derive(FromRow)]
+struct RetentionRow {
+    #[sqlx(flatten)]
+    record: CdrRow,
+    anonymized: bool,
+}
+
 pub struct PgCdrRepository {
     pool: PgPool,
 }
@@ -98,9 +177,14 @@ impl CdrRepository for PgCdrRepository {
                 status, end_reason, sip_response_code,
                 codec, rtp_packets_sent, rtp_packets_received,
                 rtp_bytes_sent, rtp_bytes_received,
+                correlation_id, dialed_number, redirect_count,
+                hold_duration, hold_count, test_call, announcement_variant,
+                early_media_time, ack_time, trunk, suspect_answer, live_transcribed, codec_fallback,
+                ring_time, ring_duration, talk_duration,
+                auth_latency_ms, routing_latency_ms, provisional_latency_ms, answer_latency_ms,
                 created_at, updated_at
             )
-            VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12, $13, $14, $15, $16, $17, $18, $19, $20, $21, $22, $23, $24, $25)
+            VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12, $13, $14, $15, $16, $17, $18, $19, $20, $21, $22, $23, $24, $25, $26, $27, $28, $29, $30, $31, $32, $33, $34, $35, $36, $37, $38, $39, $40, $41, $42, $43, $44, $45)
             "#,
             cdr.id,
             cdr.call_id,
@@ -125,6 +209,26 @@ impl CdrRepository for PgCdrRepository {
             cdr.rtp_packets_received,
             cdr.rtp_bytes_sent,
             cdr.rtp_bytes_received,
+            cdr.correlation_id,
+            cdr.dialed_number,
+            cdr.redirect_count,
+            cdr.hold_duration,
+            cdr.hold_count,
+            cdr.test_call,
+            cdr.announcement_variant,
+            cdr.early_media_time,
+            cdr.ack_time,
+            cdr.trunk,
+            cdr.suspect_answer,
+            cdr.live_transcribed,
+            cdr.codec_fallback,
+            cdr.ring_time,
+            cdr.ring_duration,
+            cdr.talk_duration,
+            cdr.auth_latency_ms,
+            cdr.routing_latency_ms,
+            cdr.provisional_latency_ms,
+            cdr.answer_latency_ms,
             cdr.created_at,
             cdr.updated_at,
         )
@@ -154,7 +258,19 @@ impl CdrRepository for PgCdrRepository {
                 status = $16, end_reason = $17, sip_response_code = $18,
                 codec = $19, rtp_packets_sent = $20, rtp_packets_received = $21,
                 rtp_bytes_sent = $22, rtp_bytes_received = $23,
-                updated_at = $24
+                correlation_id = $24,
+                dialed_number = $25,
+                redirect_count = $26,
+                hold_duration = $27, hold_count = $28,
+                test_call = $29,
+                announcement_variant = $30,
+                early_media_time = $31, ack_time = $32,
+                trunk = $33, suspect_answer = $34, live_transcribed = $35,
+                codec_fallback = $36,
+                ring_time = $37, ring_duration = $38, talk_duration = $39,
+                auth_latency_ms = $40, routing_latency_ms = $41,
+                provisional_latency_ms = $42, answer_latency_ms = $43,
+                updated_at = $44
             WHERE id = $1
             "#,
             cdr.id,
@@ -180,6 +296,26 @@ impl CdrRepository for PgCdrRepository {
             cdr.rtp_packets_received,
             cdr.rtp_bytes_sent,
             cdr.rtp_bytes_received,
+            cdr.correlation_id,
+            cdr.dialed_number,
+            cdr.redirect_count,
+            cdr.hold_duration,
+            cdr.hold_count,
+            cdr.test_call,
+            cdr.announcement_variant,
+            cdr.early_media_time,
+            cdr.ack_time,
+            cdr.trunk,
+            cdr.suspect_answer,
+            cdr.live_transcribed,
+            cdr.codec_fallback,
+            cdr.ring_time,
+            cdr.ring_duration,
+            cdr.talk_duration,
+            cdr.auth_latency_ms,
+            cdr.routing_latency_ms,
+            cdr.provisional_latency_ms,
+            cdr.answer_latency_ms,
             cdr.updated_at,
         )
         .execute(&self.pool)
@@ -212,6 +348,11 @@ impl CdrRepository for PgCdrRepository {
                 status, end_reason, sip_response_code,
                 codec, rtp_packets_sent, rtp_packets_received,
                 rtp_bytes_sent, rtp_bytes_received,
+                correlation_id, dialed_number, redirect_count,
+                hold_duration, hold_count, test_call, announcement_variant,
+                early_media_time, ack_time, trunk, suspect_answer, live_transcribed, codec_fallback,
+                ring_time, ring_duration, talk_duration,
+                auth_latency_ms, routing_latency_ms, provisional_latency_ms, answer_latency_ms,
                 created_at, updated_at
             FROM call_records
             WHERE id = $1
@@ -253,6 +394,26 @@ impl CdrRepository for PgCdrRepository {
             rtp_packets_received: r.rtp_packets_received,
             rtp_bytes_sent: r.rtp_bytes_sent,
             rtp_bytes_received: r.rtp_bytes_received,
+            correlation_id: r.correlation_id,
+            dialed_number: r.dialed_number,
+            redirect_count: r.redirect_count,
+            hold_duration: r.hold_duration,
+            hold_count: r.hold_count,
+            test_call: r.test_call,
+            announcement_variant: r.announcement_variant,
+            early_media_time: r.early_media_time,
+            ack_time: r.ack_time,
+            trunk: r.trunk,
+            suspect_answer: r.suspect_answer,
+            live_transcribed: r.live_transcribed,
+            codec_fallback: r.codec_fallback,
+            ring_time: r.ring_time,
+            ring_duration: r.ring_duration,
+            talk_duration: r.talk_duration,
+            auth_latency_ms: r.auth_latency_ms,
+            routing_latency_ms: r.routing_latency_ms,
+            provisional_latency_ms: r.provisional_latency_ms,
+            answer_latency_ms: r.answer_latency_ms,
             created_at: r.created_at,
             updated_at: r.updated_at,
         }))
@@ -273,6 +434,11 @@ impl CdrRepository for PgCdrRepository {
                 status, end_reason, sip_response_code,
                 codec, rtp_packets_sent, rtp_packets_received,
                 rtp_bytes_sent, rtp_bytes_received,
+                correlation_id, dialed_number, redirect_count,
+                hold_duration, hold_count, test_call, announcement_variant,
+                early_media_time, ack_time, trunk, suspect_answer, live_transcribed, codec_fallback,
+                ring_time, ring_duration, talk_duration,
+                auth_latency_ms, routing_latency_ms, provisional_latency_ms, answer_latency_ms,
                 created_at, updated_at
             FROM call_records
             WHERE call_id = $1
@@ -316,6 +482,26 @@ impl CdrRepository for PgCdrRepository {
             rtp_packets_received: r.rtp_packets_received,
             rtp_bytes_sent: r.rtp_bytes_sent,
             rtp_bytes_received: r.rtp_bytes_received,
+            correlation_id: r.correlation_id,
+            dialed_number: r.dialed_number,
+            redirect_count: r.redirect_count,
+            hold_duration: r.hold_duration,
+            hold_count: r.hold_count,
+            test_call: r.test_call,
+            announcement_variant: r.announcement_variant,
+            early_media_time: r.early_media_time,
+            ack_time: r.ack_time,
+            trunk: r.trunk,
+            suspect_answer: r.suspect_answer,
+            live_transcribed: r.live_transcribed,
+            codec_fallback: r.codec_fallback,
+            ring_time: r.ring_time,
+            ring_duration: r.ring_duration,
+            talk_duration: r.talk_duration,
+            auth_latency_ms: r.auth_latency_ms,
+            routing_latency_ms: r.routing_latency_ms,
+            provisional_latency_ms: r.provisional_latency_ms,
+            answer_latency_ms: r.answer_latency_ms,
             created_at: r.created_at,
             updated_at: r.updated_at,
         }))
@@ -328,6 +514,7 @@ impl CdrRepository for PgCdrRepository {
         offset: i64,
     ) -> Result<Vec<CallDetailRecord>, String> {
         debug!("Listing CDRs with filters: {:?}", filters);
+        let order = order_by(filters.sort.as_ref());
 
         // Use query_as with CdrRow to avoid type mismatch issues
         let records: Vec<CdrRow> = if filters.caller_username.is_none()
@@ -339,7 +526,7 @@ impl CdrRepository for PgCdrRepository {
             && filters.min_duration.is_none()
         {
             // No filters - simple query
-            sqlx::query_as::<_, CdrRow>(
+            sqlx::query_as::<_, CdrRow>(&format!(
                 r#"
                 SELECT
                     id, call_id,
@@ -351,19 +538,25 @@ impl CdrRepository for PgCdrRepository {
                     status, end_reason, sip_response_code,
                     codec, rtp_packets_sent, rtp_packets_received,
                     rtp_bytes_sent, rtp_bytes_received,
+                    correlation_id, dialed_number, redirect_count,
+                    hold_duration, hold_count, test_call, announcement_variant,
+                    early_media_time, ack_time, trunk, suspect_answer, live_transcribed, codec_fallback,
+                    ring_time, ring_duration, talk_duration,
+                    auth_latency_ms, routing_latency_ms, provisional_latency_ms, answer_latency_ms,
                     created_at, updated_at
                 FROM call_records
-                ORDER BY start_time DESC
+                ORDER BY {}
                 LIMIT $1 OFFSET $2
                 "#,
-            )
+                order
+            ))
             .bind(limit)
             .bind(offset)
             .fetch_all(&self.pool)
             .await
         } else if let Some(ref caller) = filters.caller_username {
             // With caller filter
-            sqlx::query_as::<_, CdrRow>(
+            sqlx::query_as::<_, CdrRow>(&format!(
                 r#"
                 SELECT
                     id, call_id,
@@ -375,21 +568,31 @@ impl CdrRepository for PgCdrRepository {
                     status, end_reason, sip_response_code,
                     codec, rtp_packets_sent, rtp_packets_received,
                     rtp_bytes_sent, rtp_bytes_received,
+                    correlation_id, dialed_number, redirect_count,
+                    hold_duration, hold_count, test_call, announcement_variant,
+                    early_media_time, ack_time, trunk, suspect_answer, live_transcribed, codec_fallback,
+                    ring_time, ring_duration, talk_duration,
+                    auth_latency_ms, routing_latency_ms, provisional_latency_ms, answer_latency_ms,
                     created_at, updated_at
                 FROM call_records
                 WHERE caller_username = $1
-                ORDER BY start_time DESC
+                  AND ($4::timestamptz IS NULL OR start_time >= $4)
+                  AND ($5::timestamptz IS NULL OR start_time < $5)
+                ORDER BY {}
                 LIMIT $2 OFFSET $3
                 "#,
-            )
+                order
+            ))
             .bind(caller)
             .bind(limit)
             .bind(offset)
+            .bind(filters.start_time_from)
+            .bind(filters.start_time_to)
             .fetch_all(&self.pool)
             .await
         } else {
-            // For other filters, use the no-filter query for now
-            sqlx::query_as::<_, CdrRow>(
+            // For other filters, only the time range is applied for now
+            sqlx::query_as::<_, CdrRow>(&format!(
                 r#"
                 SELECT
                     id, call_id,
@@ -401,14 +604,24 @@ impl CdrRepository for PgCdrRepository {
                     status, end_reason, sip_response_code,
                     codec, rtp_packets_sent, rtp_packets_received,
                     rtp_bytes_sent, rtp_bytes_received,
+                    correlation_id, dialed_number, redirect_count,
+                    hold_duration, hold_count, test_call, announcement_variant,
+                    early_media_time, ack_time, trunk, suspect_answer, live_transcribed, codec_fallback,
+                    ring_time, ring_duration, talk_duration,
+                    auth_latency_ms, routing_latency_ms, provisional_latency_ms, answer_latency_ms,
                     created_at, updated_at
                 FROM call_records
-                ORDER BY start_time DESC
+                WHERE ($3::timestamptz IS NULL OR start_time >= $3)
+                  AND ($4::timestamptz IS NULL OR start_time < $4)
+                ORDER BY {}
                 LIMIT $1 OFFSET $2
                 "#,
-            )
+                order
+            ))
             .bind(limit)
             .bind(offset)
+            .bind(filters.start_time_from)
+            .bind(filters.start_time_to)
             .fetch_all(&self.pool)
             .await
         }
@@ -437,15 +650,30 @@ impl CdrRepository for PgCdrRepository {
                 .await
         } else if let Some(ref caller) = filters.caller_username {
             sqlx::query_scalar!(
-                "SELECT COUNT(*) FROM call_records WHERE caller_username = $1",
-                caller
+                r#"
+                SELECT COUNT(*) FROM call_records
+                WHERE caller_username = $1
+                  AND ($2::timestamptz IS NULL OR start_time >= $2)
+                  AND ($3::timestamptz IS NULL OR start_time < $3)
+                "#,
+                caller,
+                filters.start_time_from,
+                filters.start_time_to
             )
             .fetch_one(&self.pool)
             .await
         } else {
-            sqlx::query_scalar!("SELECT COUNT(*) FROM call_records")
-                .fetch_one(&self.pool)
-                .await
+            sqlx::query_scalar!(
+                r#"
+                SELECT COUNT(*) FROM call_records
+                WHERE ($1::timestamptz IS NULL OR start_time >= $1)
+                  AND ($2::timestamptz IS NULL OR start_time < $2)
+                "#,
+                filters.start_time_from,
+                filters.start_time_to
+            )
+            .fetch_one(&self.pool)
+            .await
         }
         .map_err(|e| {
             error!("Failed to count CDRs: {}", e);
@@ -475,4 +703,246 @@ impl CdrRepository for PgCdrRepository {
         debug!("Deleted {} old CDRs", result.rows_affected());
         Ok(result.rows_affected() as i64)
     }
+
+    async fn list_for_user(
+        &self,
+        username: &str,
+        limit: i64,
+    ) -> Result<Vec<CallDetailRecord>, String> {
+        debug!("Listing CDRs for user: {}", username);
+
+        let records: Vec<CdrRow> = sqlx::query_as::<_, CdrRow>(
+            r#"
+            SELECT
+                id, call_id,
+                caller_username, caller_uri, caller_ip,
+                callee_username, callee_uri, callee_ip,
+                direction,
+                start_time, answer_time, end_time,
+                setup_duration, call_duration, total_duration,
+                status, end_reason, sip_response_code,
+                codec, rtp_packets_sent, rtp_packets_received,
+                rtp_bytes_sent, rtp_bytes_received,
+                correlation_id, dialed_number, redirect_count,
+                hold_duration, hold_count, test_call, announcement_variant,
+                early_media_time, ack_time, trunk, suspect_answer, live_transcribed, codec_fallback,
+                ring_time, ring_duration, talk_duration,
+                auth_latency_ms, routing_latency_ms, provisional_latency_ms, answer_latency_ms,
+                created_at, updated_at
+            FROM call_records
+            WHERE caller_username = $1
+               OR callee_username = $1
+               OR correlation_id IN (
+                    SELECT correlation_id FROM call_records
+                    WHERE (caller_username = $1 OR callee_username = $1)
+                      AND correlation_id IS NOT NULL
+               )
+            ORDER BY start_time DESC
+            LIMIT $2
+            "#,
+        )
+        .bind(username)
+        .bind(limit)
+        .fetch_all(&self.pool)
+        .await
+        .map_err(|e| {
+            error!("Failed to list CDRs for user: {}", e);
+            format!("Database error: {}", e)
+        })?;
+
+        Ok(records.into_iter().map(Into::into).collect())
+    }
+}
+
+#[async_trait]
+impl CdrRetentionRepository for PgCdrRepository {
+    async fn list_started_before(
+        &self,
+        before: DateTime<Utc>,
+        after: Option<(DateTime<Utc>, Uuid)>,
+        limit: i64,
+    ) -> Result<Vec<RetentionCandidate>, String> {
+        let (after_time, after_id) = after.unzip();
+        let rows: Vec<RetentionRow> = sqlx::query_as::<_, RetentionRow>(
+            r#"
+            SELECT
+                id, call_id,
+                caller_username, caller_uri, caller_ip,
+                callee_username, callee_uri, callee_ip,
+                direction,
+                start_time, answer_time, end_time,
+                setup_duration, call_duration, total_duration,
+                status, end_reason, sip_response_code,
+                codec, rtp_packets_sent, rtp_packets_received,
+                rtp_bytes_sent, rtp_bytes_received,
+                correlation_id, dialed_number, redirect_count,
+                hold_duration, hold_count, test_call, announcement_variant,
+                early_media_time, ack_time, trunk, suspect_answer, live_transcribed, codec_fallback,
+                ring_time, ring_duration, talk_duration,
+                auth_latency_ms, routing_latency_ms, provisional_latency_ms, answer_latency_ms,
+                created_at, updated_at, anonymized
+            FROM call_records
+            WHERE start_time < $1
+              AND ($2::timestamptz IS NULL OR (start_time, id) > ($2, $3::uuid))
+            ORDER BY start_time, id
+            LIMIT $4
+            "#,
+        )
+        .bind(before)
+        .bind(after_time)
+        .bind(after_id)
+        .bind(limit)
+        .fetch_all(&self.pool)
+        .await
+        .map_err(|e| {
+            error!("Failed to list CDRs for retention: {}", e);
+            format!("Database error: {}", e)
+        })?;
+
+        Ok(rows
+            .into_iter()
+            .map(|row| RetentionCandidate {
+                cdr: row.record.into(),
+                anonymized: row.anonymized,
+            })
+            .collect())
+    }
+
+    async fn delete(&self, ids: &[Uuid]) -> Result<u64, String> {
+        let result = sqlx::query("DELETE FROM call_records WHERE id = ANY($1)")
+            .bind(ids)
+            .execute(&self.pool)
+            .await
+            .map_err(|e| {
+                error!("Failed to delete expired CDRs: {}", e);
+                format!("Database error: {}", e)
+            })?;
+
+        debug!("Deleted {} expired CDRs", result.rows_affected());
+        Ok(result.rows_affected())
+    }
+
+    async fn anonymize(&self, ids: &[Uuid]) -> Result<u64, String> {
+        let result = sqlx::query(
+            r#"
+            UPDATE call_records
+            SET call_id = id::text,
+                caller_username = 'anonymous',
+                caller_uri = 'sip:anonymous@anonymous.invalid',
+                caller_ip = '0.0.0.0',
+                callee_username = 'anonymous',
+                callee_uri = 'sip:anonymous@anonymous.invalid',
+                callee_ip = NULL,
+                dialed_number = NULL,
+                anonymized = TRUE
+            WHERE id = ANY($1) AND NOT anonymized
+            "#,
+        )
+        .bind(ids)
+        .execute(&self.pool)
+        .await
+        .map_err(|e| {
+            error!("Failed to anonymize expired CDRs: {}", e);
+            format!("Database error: {}", e)
+        })?;
+
+        debug!("Anonymized {} expired CDRs", result.rows_affected());
+        Ok(result.rows_affected())
+    }
+
+    async fn ensure_partition(&self, month: NaiveDate) -> Result<Option<String>, String> {
+        sqlx::query_scalar::<_, Option<String>>("SELECT create_call_records_partition($1)")
+            .bind(month)
+            .fetch_one(&self.pool)
+            .await
+            .map_err(|e| {
+                error!("Failed to create CDR partition for {}: {}", month, e);
+                format!("Database error: {}", e)
+            })
+    }
+}
+
+#[async_trait]
+impl SubjectStore for PgCdrRepository {
+    fn name(&self) -> &'static str {
+        "cdrs"
+    }
+
+    fn subject_fields(&self) -> &'static [&'static str] {
+        CallDetailRecord::SUBJECT_FIELDS
+    }
+
+    async fn find_subject(&self, subject: &ErasureSubject) -> Result<Vec<SubjectReference>, String> {
+        let rows: Vec<CdrRow> = sqlx::query_as::<_, CdrRow>(&format!(
+            "SELECT {} FROM call_records
+             WHERE caller_username ILIKE ANY($1) OR caller_uri ILIKE ANY($1)
+                OR callee_username ILIKE ANY($1) OR callee_uri ILIKE ANY($1)
+                OR dialed_number ILIKE ANY($1)",
+            CDR_COLUMNS
+        ))
+        .bind(subject.search_patterns())
+        .fetch_all(&self.pool)
+        .await
+        .map_err(|e| {
+            error!("Failed to search CDRs for an erasure subject: {}", e);
+            format!("Database error: {}", e)
+        })?;
+
+        Ok(rows
+            .into_iter()
+            .map(CallDetailRecord::from)
+            .filter_map(|cdr| SubjectReference::to(&cdr, cdr.id, cdr.start_time, subject))
+            .collect())
+    }
+
+    async fn pseudonymize(
+        &self,
+        subject: &ErasureSubject,
+        pseudonyms: &Pseudonymizer,
+        records: &[SubjectReference],
+    ) -> Result<u64, String> {
+        let ids: Vec<Uuid> = records
+            .iter()
+            .filter_map(|r| r.record_id.parse().ok())
+            .collect();
+        let rows: Vec<CdrRow> = sqlx::query_as::<_, CdrRow>(&format!(
+            "SELECT {} FROM call_records WHERE id = ANY($1)",
+            CDR_COLUMNS
+        ))
+        .bind(&ids)
+        .fetch_all(&self.pool)
+        .await
+        .map_err(|e| format!("Database error: {}", e))?;
+
+        let mut count = 0;
+        for mut cdr in rows.into_iter().map(CallDetailRecord::from) {
+            if !cdr.pseudonymize(subject, pseudonyms) {
+                continue;
+            }
+            sqlx::query(
+                r#"
+                UPDATE call_records
+                SET caller_username = $2, caller_uri = $3,
+                    callee_username = $4, callee_uri = $5,
+                    dialed_number = $6, updated_at = NOW()
+                WHERE id = $1
+                "#,
+            )
+            .bind(cdr.id)
+            .bind(&cdr.caller_username)
+            .bind(&cdr.caller_uri)
+            .bind(&cdr.callee_username)
+            .bind(&cdr.callee_uri)
+            .bind(&cdr.dialed_number)
+            .execute(&self.pool)
+            .await
+            .map_err(|e| {
+                error!("Failed to pseudonymize CDR {}: {}", cdr.id, e);
+                format!("Database error: {}", e)
+            })?;
+            count += 1;
+        }
+        debug!("Pseudonymized {} CDRs", count);
+        Ok(count)
+    }
 }
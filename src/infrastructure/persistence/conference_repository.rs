@@ -94,6 +94,8 @@ impl ConferenceRepository for PgConferenceRepository {
                     created_at: row.get("created_at"),
                     started_at: row.get("started_at"),
                     ended_at: row.get("ended_at"),
+                    floor_control: None,
+                    entry_exit: Default::default(),
                 };
 
                 Ok(Some(room))
@@ -214,6 +216,8 @@ impl ConferenceRepository for PgConferenceRepository {
                             created_at: row.get("created_at"),
                             started_at: row.get("started_at"),
                             ended_at: row.get("ended_at"),
+                            floor_control: None,
+                            entry_exit: Default::default(),
                         }
                     })
                     .collect();
@@ -335,6 +339,8 @@ impl ConferenceRepository for PgConferenceRepository {
                             volume: row.get("volume"),
                             joined_at: row.get("joined_at"),
                             left_at: row.get("left_at"),
+                            floor: Default::default(),
+                            name_recording: None,
                         }
                     })
                     .collect();
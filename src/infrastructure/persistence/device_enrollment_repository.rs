@@ -0,0 +1,190 @@
+//! PostgreSQL implementation of DeviceEnrollmentRepository
+
+use crate::domain::device_enrollment::{DeviceCredential, DeviceEnrollmentRepository, Enrollment};
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use sqlx::{FromRow, PgPool};
+use tracing::{debug, error};
+use uuid::Uuid;
+
+#[derive(FromRow)]
+struct EnrollmentRow {
+    id: Uuid,
+    user_id: i32,
+    token_hash: String,
+    label: Option<String>,
+    max_uses: i32,
+    uses: i32,
+    expires_at: DateTime<Utc>,
+    created_at: DateTime<Utc>,
+}
+
+impl From<EnrollmentRow> for Enrollment {
+    fn from(r: EnrollmentRow) -> Self {
+        Enrollment {
+            id: r.id,
+            user_id: r.user_id,
+            token_hash: r.token_hash,
+            label: r.label,
+            max_uses: r.max_uses.max(1) as u32,
+            uses: r.uses.max(0) as u32,
+            expires_at: r.expires_at,
+            created_at: r.created_at,
+        }
+    }
+}
+
+#[derive(FromRow)]
+struct DeviceCredentialRow {
+    id: Uuid,
+    user_id: i32,
+    enrollment_id: Option<Uuid>,
+    label: Option<String>,
+    sip_ha1: String,
+    created_at: DateTime<Utc>,
+    revoked_at: Option<DateTime<Utc>>,
+}
+
+impl From<DeviceCredentialRow> for DeviceCredential {
+    fn from(r: DeviceCredentialRow) -> Self {
+        DeviceCredential {
+            id: r.id,
+            user_id: r.user_id,
+            enrollment_id: r.enrollment_id,
+            label: r.label,
+            sip_ha1: r.sip_ha1,
+            created_at: r.created_at,
+            revoked_at: r.revoked_at,
+        }
+    }
+}
+
+pub struct PgDeviceEnrollmentRepository {
+    pool: PgPool,
+}
+
+impl PgDeviceEnrollmentRepository {
+    pub fn new(pool: PgPool) -> Self {
+        Self { pool }
+    }
+}
+
+#[async_trait]
+impl DeviceEnrollmentRepository for PgDeviceEnrollmentRepository {
+    async fn create_enrollment(&self, enrollment: &Enrollment) -> Result<(), String> {
+        debug!("Creating enrollment {} for user {}", enrollment.id, enrollment.user_id);
+
+        sqlx::query(
+            r#"
+            INSERT INTO device_enrollments
+                (id, user_id, token_hash, label, max_uses, uses, expires_at, created_at)
+            VALUES ($1, $2, $3, $4, $5, $6, $7, $8)
+            "#,
+        )
+        .bind(enrollment.id)
+        .bind(enrollment.user_id)
+        .bind(&enrollment.token_hash)
+        .bind(&enrollment.label)
+        .bind(enrollment.max_uses as i32)
+        .bind(enrollment.uses as i32)
+        .bind(enrollment.expires_at)
+        .bind(enrollment.created_at)
+        .execute(&self.pool)
+        .await
+        .map_err(|e| {
+            error!("Failed to create enrollment: {}", e);
+            format!("Database error: {}", e)
+        })?;
+
+        Ok(())
+    }
+
+    async fn redeem(&self, token_hash: &str, at: DateTime<Utc>) -> Result<Option<Enrollment>, String> {
+        // One statement, so concurrent redemptions cannot exceed max_uses
+        sqlx::query_as::<_, EnrollmentRow>(
+            r#"
+            UPDATE device_enrollments
+            SET uses = uses + 1
+            WHERE token_hash = $1 AND uses < max_uses AND expires_at > $2
+            RETURNING id, user_id, token_hash, label, max_uses, uses, expires_at, created_at
+            "#,
+        )
+        .bind(token_hash)
+        .bind(at)
+        .fetch_optional(&self.pool)
+        .await
+        .map(|row| row.map(Into::into))
+        .map_err(|e| {
+            error!("Failed to redeem enrollment: {}", e);
+            format!("Database error: {}", e)
+        })
+    }
+
+    async fn create_credential(&self, credential: &DeviceCredential) -> Result<(), String> {
+        debug!("Creating device credential {} for user {}", credential.id, credential.user_id);
+
+        sqlx::query(
+            r#"
+            INSERT INTO device_credentials
+                (id, user_id, enrollment_id, label, sip_ha1, created_at, revoked_at)
+            VALUES ($1, $2, $3, $4, $5, $6, $7)
+            "#,
+        )
+        .bind(credential.id)
+        .bind(credential.user_id)
+        .bind(credential.enrollment_id)
+        .bind(&credential.label)
+        .bind(&credential.sip_ha1)
+        .bind(credential.created_at)
+        .bind(credential.revoked_at)
+        .execute(&self.pool)
+        .await
+        .map_err(|e| {
+            error!("Failed to create device credential: {}", e);
+            format!("Database error: {}", e)
+        })?;
+
+        Ok(())
+    }
+
+    async fn list_credentials(&self, user_id: i32) -> Result<Vec<DeviceCredential>, String> {
+        let rows = sqlx::query_as::<_, DeviceCredentialRow>(
+            r#"
+            SELECT id, user_id, enrollment_id, label, sip_ha1, created_at, revoked_at
+            FROM device_credentials
+            WHERE user_id = $1
+            ORDER BY created_at
+            "#,
+        )
+        .bind(user_id)
+        .fetch_all(&self.pool)
+        .await
+        .map_err(|e| {
+            error!("Failed to list device credentials of user {}: {}", user_id, e);
+            format!("Database error: {}", e)
+        })?;
+
+        Ok(rows.into_iter().map(Into::into).collect())
+    }
+
+    async fn revoke_credential(&self, user_id: i32, id: Uuid, at: DateTime<Utc>) -> Result<bool, String> {
+        let result = sqlx::query(
+            r#"
+            UPDATE device_credentials
+            SET revoked_at = $3
+            WHERE id = $1 AND user_id = $2 AND revoked_at IS NULL
+            "#,
+        )
+        .bind(id)
+        .bind(user_id)
+        .bind(at)
+        .execute(&self.pool)
+        .await
+        .map_err(|e| {
+            error!("Failed to revoke device credential {}: {}", id, e);
+            format!("Database error: {}", e)
+        })?;
+
+        Ok(result.rows_affected() > 0)
+    }
+}
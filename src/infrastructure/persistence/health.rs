@@ -0,0 +1,342 @@
+//! Database health tracking and degraded mode
+//!
+//! A short Postgres failover should not turn into a re-registration storm.
+//! `DbHealth` tracks whether the pool is currently usable; while it is not,
+//! the server runs in a read-only *degraded* mode: digest auth falls back to
+//! cached HA1s, CDR writes are queued, and API mutations return 503.
+
+use chrono::{DateTime, Utc};
+use metrics::gauge;
+use serde::{Deserialize, Serialize};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::sync::watch;
+use tracing::{info, warn};
+
+/// Classification of a database error
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DbErrorClass {
+    /// Connectivity or failover problem that is expected to clear on its own
+    Transient,
+    /// Query, constraint or data problem that retrying will not fix
+    Permanent,
+}
+
+/// Message fragments produced by sqlx / Postgres for failover-type errors
+const TRANSIENT_PATTERNS: &[&str] = &[
+    "pool timed out",
+    "closed pool",
+    "error communicating with database",
+    "connection refused",
+    "connection reset",
+    "broken pipe",
+    "timed out",
+    "the database system is starting up",
+    "the database system is shutting down",
+    "the database system is in recovery mode",
+    "terminating connection due to administrator command",
+    "read-only transaction",
+    "could not connect to server",
+];
+
+/// Classify a repository error message as transient or permanent
+///
+/// Repositories surface errors as strings, so classification is done on the
+/// rendered sqlx error text.
+pub fn classify_db_error(message: &str) -> DbErrorClass {
+    let lower = message.to_ascii_lowercase();
+    if TRANSIENT_PATTERNS.iter().any(|p| lower.contains(p)) {
+        DbErrorClass::Transient
+    } else {
+        DbErrorClass::Permanent
+    }
+}
+
+/// Classify a sqlx error as transient or permanent
+#[cfg(feature = "postgres")]
+pub fn classify_sqlx_error(error: &sqlx::Error) -> DbErrorClass {
+    match error {
+        sqlx::Error::Io(_)
+        | sqlx::Error::Tls(_)
+        | sqlx::Error::PoolTimedOut
+        | sqlx::Error::PoolClosed
+        | sqlx::Error::WorkerCrashed => DbErrorClass::Transient,
+        sqlx::Error::Database(db_err) => match db_err.code().as_deref() {
+            // admin_shutdown, crash_shutdown, cannot_connect_now,
+            // read_only_sql_transaction (writing to a demoted primary)
+            Some("57P01") | Some("57P02") | Some("57P03") | Some("25006") => {
+                DbErrorClass::Transient
+            }
+            Some(code) if code.starts_with("08") => DbErrorClass::Transient,
+            _ => DbErrorClass::Permanent,
+        },
+        _ => DbErrorClass::Permanent,
+    }
+}
+
+/// Database operating mode
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum DbMode {
+    Normal,
+    Degraded,
+}
+
+/// Health monitor configuration
+#[derive(Debug, Clone)]
+pub struct DbHealthConfig {
+    /// Interval between background pool probes
+    pub check_interval: Duration,
+    /// Timeout for a single probe
+    pub probe_timeout: Duration,
+    /// Consecutive failed probes before entering degraded mode
+    pub failure_threshold: u32,
+    /// Retry-After hint returned to API clients while degraded
+    pub retry_after: Duration,
+}
+
+impl Default for DbHealthConfig {
+    fn default() -> Self {
+        Self {
+            check_interval: Duration::from_secs(2),
+            probe_timeout: Duration::from_secs(2),
+            failure_threshold: 2,
+            retry_after: Duration::from_secs(5),
+        }
+    }
+}
+
+#[derive(Debug)]
+struct HealthState {
+    consecutive_failures: u32,
+    degraded_since: Option<DateTime<Utc>>,
+    last_error: Option<String>,
+}
+
+/// Shared database health state
+pub struct DbHealth {
+    config: DbHealthConfig,
+    state: Mutex<HealthState>,
+    mode_tx: watch::Sender<DbMode>,
+}
+
+impl DbHealth {
+    /// Create a new health tracker in normal mode
+    pub fn new(config: DbHealthConfig) -> Self {
+        let (mode_tx, _) = watch::channel(DbMode::Normal);
+        gauge!("db_degraded_mode").set(0.0);
+        Self {
+            config,
+            state: Mutex::new(HealthState {
+                consecutive_failures: 0,
+                degraded_since: None,
+                last_error: None,
+            }),
+            mode_tx,
+        }
+    }
+
+    /// Get the configuration
+    pub fn config(&self) -> &DbHealthConfig {
+        &self.config
+    }
+
+    /// Current operating mode
+    pub fn mode(&self) -> DbMode {
+        *self.mode_tx.borrow()
+    }
+
+    /// Whether the server is currently in degraded mode
+    pub fn is_degraded(&self) -> bool {
+        self.mode() == DbMode::Degraded
+    }
+
+    /// When degraded mode was entered, if currently degraded
+    pub fn degraded_since(&self) -> Option<DateTime<Utc>> {
+        self.state.lock().unwrap().degraded_since
+    }
+
+    /// Last transient error observed
+    pub fn last_error(&self) -> Option<String> {
+        self.state.lock().unwrap().last_error.clone()
+    }
+
+    /// Retry-After hint in seconds
+    pub fn retry_after_secs(&self) -> u64 {
+        self.config.retry_after.as_secs().max(1)
+    }
+
+    /// Subscribe to mode changes
+    pub fn subscribe(&self) -> watch::Receiver<DbMode> {
+        self.mode_tx.subscribe()
+    }
+
+    /// Record a failed probe; enters degraded mode once the threshold is hit
+    pub fn record_failure(&self, error: &str) {
+        let mut state = self.state.lock().unwrap();
+        state.consecutive_failures += 1;
+        state.last_error = Some(error.to_string());
+
+        if state.consecutive_failures >= self.config.failure_threshold {
+            self.enter_degraded(&mut state);
+        }
+    }
+
+    /// Report a transient error seen by a repository call
+    ///
+    /// A caller that has just hit a failover error knows the pool is unusable,
+    /// so this enters degraded mode immediately instead of waiting for probes.
+    pub fn report_transient(&self, error: &str) {
+        let mut state = self.state.lock().unwrap();
+        state.last_error = Some(error.to_string());
+        state.consecutive_failures = state.consecutive_failures.max(self.config.failure_threshold);
+        self.enter_degraded(&mut state);
+    }
+
+    /// Record a successful probe; leaves degraded mode if active
+    pub fn record_success(&self) {
+        let mut state = self.state.lock().unwrap();
+        state.consecutive_failures = 0;
+
+        if state.degraded_since.take().is_some() {
+            state.last_error = None;
+            info!("Database recovered, leaving degraded mode");
+            gauge!("db_degraded_mode").set(0.0);
+            self.mode_tx.send_replace(DbMode::Normal);
+        }
+    }
+
+    fn enter_degraded(&self, state: &mut HealthState) {
+        if state.degraded_since.is_none() {
+            state.degraded_since = Some(Utc::now());
+            warn!(
+                "Database unavailable, entering degraded mode: {}",
+                state.last_error.as_deref().unwrap_or("unknown error")
+            );
+            gauge!("db_degraded_mode").set(1.0);
+            self.mode_tx.send_replace(DbMode::Degraded);
+        }
+    }
+}
+
+impl Default for DbHealth {
+    fn default() -> Self {
+        Self::new(DbHealthConfig::default())
+    }
+}
+
+/// Spawn a background task that probes the pool and updates `health`
+#[cfg(feature = "postgres")]
+pub fn spawn_pool_monitor(
+    pool: sqlx::PgPool,
+    health: Arc<DbHealth>,
+) -> tokio::task::JoinHandle<()> {
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(health.config().check_interval);
+        loop {
+            interval.tick().await;
+
+            let probe = sqlx::query("SELECT 1").execute(&pool);
+            match tokio::time::timeout(health.config().probe_timeout, probe).await {
+                Ok(Ok(_)) => health.record_success(),
+                Ok(Err(e)) => match classify_sqlx_error(&e) {
+                    DbErrorClass::Transient => health.record_failure(&e.to_string()),
+                    // The pool answered; the probe itself is what failed
+                    DbErrorClass::Permanent => health.record_success(),
+                },
+                Err(_) => health.record_failure("health probe timed out"),
+            }
+        }
+    })
+}
+
+/// Wait until `health` leaves degraded mode and run `on_recover` each time
+pub fn spawn_recovery_listener<F, Fut>(
+    health: Arc<DbHealth>,
+    on_recover: F,
+) -> tokio::task::JoinHandle<()>
+where
+    F: Fn() -> Fut + Send + 'static,
+    Fut: std::future::Future<Output = ()> + Send,
+{
+    let mut rx = health.subscribe();
+    let mut previous = *rx.borrow_and_update();
+    tokio::spawn(async move {
+        while rx.changed().await.is_ok() {
+            let current = *rx.borrow();
+            if previous == DbMode::Degraded && current == DbMode::Normal {
+                on_recover().await;
+            }
+            previous = current;
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_classify_db_error() {
+        assert_eq!(
+            classify_db_error("Failed to find user: pool timed out while waiting for an open connection"),
+            DbErrorClass::Transient
+        );
+        assert_eq!(
+            classify_db_error("error returned from database: cannot execute INSERT in a read-only transaction"),
+            DbErrorClass::Transient
+        );
+        assert_eq!(
+            classify_db_error("duplicate key value violates unique constraint"),
+            DbErrorClass::Permanent
+        );
+    }
+
+    #[test]
+    fn test_failure_threshold_and_recovery() {
+        let health = DbHealth::new(DbHealthConfig {
+            failure_threshold: 2,
+            ..Default::default()
+        });
+
+        health.record_failure("connection refused");
+        assert_eq!(health.mode(), DbMode::Normal);
+
+        health.record_failure("connection refused");
+        assert!(health.is_degraded());
+        assert!(health.degraded_since().is_some());
+
+        health.record_success();
+        assert_eq!(health.mode(), DbMode::Normal);
+        assert!(health.degraded_since().is_none());
+    }
+
+    #[test]
+    fn test_report_transient_degrades_immediately() {
+        let health = DbHealth::default();
+        health.report_transient("pool timed out");
+        assert!(health.is_degraded());
+        assert_eq!(health.last_error().as_deref(), Some("pool timed out"));
+    }
+
+    #[tokio::test]
+    async fn test_recovery_listener_runs_on_recover() {
+        let health = Arc::new(DbHealth::default());
+        let (tx, mut rx) = tokio::sync::mpsc::unbounded_channel();
+
+        spawn_recovery_listener(health.clone(), move || {
+            let tx = tx.clone();
+            async move {
+                let _ = tx.send(());
+            }
+        });
+
+        health.report_transient("connection reset");
+        tokio::task::yield_now().await;
+        health.record_success();
+
+        tokio::time::timeout(Duration::from_secs(1), rx.recv())
+            .await
+            .expect("recovery callback not invoked");
+    }
+}
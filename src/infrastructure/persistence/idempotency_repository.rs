@@ -0,0 +1,91 @@
+//! PostgreSQL implementation of IdempotencyRepository
+
+use crate::infrastructure::idempotency::{IdempotencyRepository, StoredResponse};
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use sqlx::{FromRow, PgPool};
+use tracing::error;
+
+#[derive(FromRow)]
+struct IdempotencyRow {
+    fingerprint: String,
+    status: i16,
+    content_type: Option<String>,
+    body: Vec<u8>,
+    expires_at: DateTime<Utc>,
+}
+
+impl From<IdempotencyRow> for StoredResponse {
+    fn from(row: IdempotencyRow) -> Self {
+        Self {
+            fingerprint: row.fingerprint,
+            status: row.status as u16,
+            content_type: row.content_type,
+            body: row.body,
+            expires_at: row.expires_at,
+        }
+    }
+}
+
+fn database_error(action: &str, e: sqlx::Error) -> String {
+    error!("Failed to {}: {}", action, e);
+    format!("Database error: {}", e)
+}
+
+pub struct PgIdempotencyRepository {
+    pool: PgPool,
+}
+
+impl PgIdempotencyRepository {
+    pub fn new(pool: PgPool) -> Self {
+        Self { pool }
+    }
+}
+
+#[async_trait]
+impl IdempotencyRepository for PgIdempotencyRepository {
+    async fn get(&self, key: &str) -> Result<Option<StoredResponse>, String> {
+        sqlx::query_as::<_, IdempotencyRow>(
+            "SELECT fingerprint, status, content_type, body, expires_at FROM idempotency_keys WHERE key = $1",
+        )
+        .bind(key)
+        .fetch_optional(&self.pool)
+        .await
+        .map(|row| row.map(StoredResponse::from))
+        .map_err(|e| database_error("load idempotency key", e))
+    }
+
+    async fn save(&self, key: &str, response: &StoredResponse) -> Result<(), String> {
+        sqlx::query(
+            r#"
+            INSERT INTO idempotency_keys (key, fingerprint, status, content_type, body, expires_at)
+            VALUES ($1, $2, $3, $4, $5, $6)
+            ON CONFLICT (key) DO UPDATE SET
+                fingerprint = EXCLUDED.fingerprint,
+                status = EXCLUDED.status,
+                content_type = EXCLUDED.content_type,
+                body = EXCLUDED.body,
+                expires_at = EXCLUDED.expires_at
+            "#,
+        )
+        .bind(key)
+        .bind(&response.fingerprint)
+        .bind(response.status as i16)
+        .bind(response.content_type.as_deref())
+        .bind(&response.body)
+        .bind(response.expires_at)
+        .execute(&self.pool)
+        .await
+        .map(|_| ())
+        .map_err(|e| database_error("save idempotency key", e))
+    }
+
+    async fn purge_expired(&self, now: DateTime<Utc>) -> Result<u64, String> {
+        sqlx::query("DELETE FROM idempotency_keys WHERE expires_at <= $1")
+            .bind(now)
+            .execute(&self.pool)
+            .await
+            .map(|result| result.rows_affected())
+            .map_err(|e| database_error("purge idempotency keys", e))
+    }
+}
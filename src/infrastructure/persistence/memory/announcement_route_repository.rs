@@ -0,0 +1,71 @@
+//! In-memory AnnouncementRouteRepository
+//!
+//! Used when the server runs without a database; routes are lost on
+//! restart.
+
+use crate::domain::routing::{AnnouncementRoute, AnnouncementRouteRepository};
+use async_trait::async_trait;
+use std::sync::Mutex;
+use uuid::Uuid;
+
+/// Routes kept in creation order
+#[derive(Default)]
+pub struct MemoryAnnouncementRouteRepository {
+    routes: Mutex<Vec<AnnouncementRoute>>,
+}
+
+impl MemoryAnnouncementRouteRepository {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+#[async_trait]
+impl AnnouncementRouteRepository for MemoryAnnouncementRouteRepository {
+    async fn create(&self, route: &AnnouncementRoute) -> Result<(), String> {
+        self.routes.lock().unwrap().push(route.clone());
+        Ok(())
+    }
+
+    async fn get_by_id(&self, id: Uuid) -> Result<Option<AnnouncementRoute>, String> {
+        let routes = self.routes.lock().unwrap();
+        Ok(routes.iter().find(|r| r.id == id).cloned())
+    }
+
+    async fn find_by_number(
+        &self,
+        tenant: &str,
+        number: &str,
+    ) -> Result<Option<AnnouncementRoute>, String> {
+        let routes = self.routes.lock().unwrap();
+        Ok(routes
+            .iter()
+            .find(|r| r.tenant == tenant && r.number == number)
+            .cloned())
+    }
+
+    async fn list(&self) -> Result<Vec<AnnouncementRoute>, String> {
+        Ok(self.routes.lock().unwrap().clone())
+    }
+
+    async fn update(&self, route: &AnnouncementRoute) -> Result<(), String> {
+        let mut routes = self.routes.lock().unwrap();
+        match routes.iter_mut().find(|r| r.id == route.id) {
+            Some(stored) => {
+                *stored = route.clone();
+                Ok(())
+            }
+            None => Err(format!("Announcement route not found: {}", route.id)),
+        }
+    }
+
+    async fn delete(&self, id: Uuid) -> Result<(), String> {
+        let mut routes = self.routes.lock().unwrap();
+        let before = routes.len();
+        routes.retain(|r| r.id != id);
+        if routes.len() == before {
+            return Err(format!("Announcement route not found: {}", id));
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,68 @@
+//! In-memory AutoAttendantRepository
+//!
+//! Used when the server runs without a database; attendants are lost on
+//! restart.
+
+use crate::domain::routing::{AutoAttendant, AutoAttendantRepository};
+use async_trait::async_trait;
+use std::sync::Mutex;
+use uuid::Uuid;
+
+/// Attendants kept in creation order
+#[derive(Default)]
+pub struct MemoryAutoAttendantRepository {
+    attendants: Mutex<Vec<AutoAttendant>>,
+}
+
+impl MemoryAutoAttendantRepository {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+#[async_trait]
+impl AutoAttendantRepository for MemoryAutoAttendantRepository {
+    async fn create(&self, attendant: &AutoAttendant) -> Result<(), String> {
+        self.attendants.lock().unwrap().push(attendant.clone());
+        Ok(())
+    }
+
+    async fn get_by_id(&self, id: Uuid) -> Result<Option<AutoAttendant>, String> {
+        let attendants = self.attendants.lock().unwrap();
+        Ok(attendants.iter().find(|a| a.id == id).cloned())
+    }
+
+    async fn find_by_number(&self, tenant: &str, number: &str) -> Result<Vec<AutoAttendant>, String> {
+        let attendants = self.attendants.lock().unwrap();
+        Ok(attendants
+            .iter()
+            .filter(|a| a.tenant == tenant && a.numbers.iter().any(|n| n.number == number))
+            .cloned()
+            .collect())
+    }
+
+    async fn list(&self) -> Result<Vec<AutoAttendant>, String> {
+        Ok(self.attendants.lock().unwrap().clone())
+    }
+
+    async fn update(&self, attendant: &AutoAttendant) -> Result<(), String> {
+        let mut attendants = self.attendants.lock().unwrap();
+        match attendants.iter_mut().find(|a| a.id == attendant.id) {
+            Some(stored) => {
+                *stored = attendant.clone();
+                Ok(())
+            }
+            None => Err(format!("Auto-attendant not found: {}", attendant.id)),
+        }
+    }
+
+    async fn delete(&self, id: Uuid) -> Result<(), String> {
+        let mut attendants = self.attendants.lock().unwrap();
+        let before = attendants.len();
+        attendants.retain(|a| a.id != id);
+        if attendants.len() == before {
+            return Err(format!("Auto-attendant not found: {}", id));
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,84 @@
+//! In-memory CallRepository
+//!
+//! Used when the server runs without a database; calls are lost on restart.
+
+use crate::domain::call::{Call, CallRepository};
+use crate::domain::shared::error::DomainError;
+use crate::domain::shared::result::Result;
+use crate::domain::shared::value_objects::{CallId, EndpointId, SipUri};
+use async_trait::async_trait;
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+/// Calls by id, at their stored version
+#[derive(Default)]
+pub struct MemoryCallRepository {
+    calls: Mutex<HashMap<CallId, Call>>,
+}
+
+impl MemoryCallRepository {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+fn is_party(call: &Call, aor: &SipUri) -> bool {
+    call.caller().uri().address_of_record() == *aor || call.callee().uri().address_of_record() == *aor
+}
+
+#[async_trait]
+impl CallRepository for MemoryCallRepository {
+    async fn find_by_id(&self, id: &CallId) -> Result<Option<Call>> {
+        Ok(self.calls.lock().unwrap().get(id).cloned())
+    }
+
+    async fn save(&self, call: &mut Call) -> Result<()> {
+        let mut calls = self.calls.lock().unwrap();
+        let stored = calls.get(call.id()).map_or(0, Call::version);
+        if stored != call.version() {
+            return Err(DomainError::Conflict(format!(
+                "Call {} is at version {}, not {}",
+                call.id(),
+                stored,
+                call.version()
+            )));
+        }
+        call.set_version(stored + 1);
+        let mut copy = call.clone();
+        // Pending events belong to the caller, not the stored call
+        copy.take_events();
+        calls.insert(*call.id(), copy);
+        Ok(())
+    }
+
+    async fn delete(&self, id: &CallId) -> Result<()> {
+        match self.calls.lock().unwrap().remove(id) {
+            Some(_) => Ok(()),
+            None => Err(DomainError::NotFound(format!("Call {}", id))),
+        }
+    }
+
+    async fn find_active_calls(&self) -> Result<Vec<Call>> {
+        let calls = self.calls.lock().unwrap();
+        Ok(calls.values().filter(|c| c.is_active()).cloned().collect())
+    }
+
+    async fn find_by_endpoint(&self, endpoint_id: &EndpointId) -> Result<Vec<Call>> {
+        let calls = self.calls.lock().unwrap();
+        Ok(calls
+            .values()
+            .filter(|c| c.caller().endpoint_id() == endpoint_id || c.callee().endpoint_id() == endpoint_id)
+            .cloned()
+            .collect())
+    }
+
+    async fn find_active_by_participant(&self, uri: &SipUri) -> Result<Vec<Call>> {
+        let aor = uri.address_of_record();
+        let calls = self.calls.lock().unwrap();
+        Ok(calls
+            .values()
+            .filter(|c| c.is_active() && is_party(c, &aor))
+            .cloned()
+            .collect())
+    }
+}
@@ -0,0 +1,173 @@
+//! In-memory CdrRepository
+//!
+//! Used by the test server; records are lost on restart.
+
+use crate::domain::cdr::{CallDetailRecord, CdrFilters, CdrRepository};
+use crate::domain::privacy::{
+    ErasureSubject, Pseudonymizer, SubjectBearing, SubjectReference, SubjectStore,
+};
+use async_trait::async_trait;
+use chrono::{Duration, Utc};
+use std::sync::Mutex;
+use uuid::Uuid;
+
+/// CDRs kept in creation order
+#[derive(Default)]
+pub struct MemoryCdrRepository {
+    cdrs: Mutex<Vec<CallDetailRecord>>,
+}
+
+impl MemoryCdrRepository {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Every stored CDR, in creation order
+    pub fn all(&self) -> Vec<CallDetailRecord> {
+        self.cdrs.lock().unwrap().clone()
+    }
+}
+
+fn matches(filters: &CdrFilters, cdr: &CallDetailRecord) -> bool {
+    filters
+        .caller_username
+        .as_ref()
+        .is_none_or(|caller| &cdr.caller_username == caller)
+        && filters
+            .callee_username
+            .as_ref()
+            .is_none_or(|callee| &cdr.callee_username == callee)
+        && filters.direction.is_none_or(|direction| cdr.direction == direction)
+        && filters.status.is_none_or(|status| cdr.status == status)
+        && filters.start_time_from.is_none_or(|from| cdr.start_time >= from)
+        && filters.start_time_to.is_none_or(|to| cdr.start_time <= to)
+        && filters
+            .min_duration
+            .is_none_or(|min| cdr.call_duration.unwrap_or(0) >= min)
+}
+
+#[async_trait]
+impl CdrRepository for MemoryCdrRepository {
+    async fn create(&self, cdr: &CallDetailRecord) -> Result<(), String> {
+        self.cdrs.lock().unwrap().push(cdr.clone());
+        Ok(())
+    }
+
+    async fn update(&self, cdr: &CallDetailRecord) -> Result<(), String> {
+        let mut cdrs = self.cdrs.lock().unwrap();
+        match cdrs.iter_mut().find(|c| c.id == cdr.id) {
+            Some(stored) => {
+                *stored = cdr.clone();
+                Ok(())
+            }
+            None => Err(format!("CDR not found: {}", cdr.id)),
+        }
+    }
+
+    async fn get_by_id(&self, id: Uuid) -> Result<Option<CallDetailRecord>, String> {
+        let cdrs = self.cdrs.lock().unwrap();
+        Ok(cdrs.iter().find(|c| c.id == id).cloned())
+    }
+
+    async fn get_by_call_id(&self, call_id: &str) -> Result<Option<CallDetailRecord>, String> {
+        let cdrs = self.cdrs.lock().unwrap();
+        Ok(cdrs.iter().find(|c| c.call_id == call_id).cloned())
+    }
+
+    /// Newest first; `filters.sort` is not applied
+    async fn list(
+        &self,
+        filters: CdrFilters,
+        limit: i64,
+        offset: i64,
+    ) -> Result<Vec<CallDetailRecord>, String> {
+        let cdrs = self.cdrs.lock().unwrap();
+        let mut listed: Vec<CallDetailRecord> =
+            cdrs.iter().filter(|c| matches(&filters, c)).cloned().collect();
+        listed.sort_by_key(|c| std::cmp::Reverse(c.start_time));
+        Ok(listed
+            .into_iter()
+            .skip(offset.max(0) as usize)
+            .take(limit.max(0) as usize)
+            .collect())
+    }
+
+    async fn count(&self, filters: CdrFilters) -> Result<i64, String> {
+        let cdrs = self.cdrs.lock().unwrap();
+        Ok(cdrs.iter().filter(|c| matches(&filters, c)).count() as i64)
+    }
+
+    async fn delete_older_than(&self, days: i32) -> Result<i64, String> {
+        let cutoff = Utc::now() - Duration::days(days as i64);
+        let mut cdrs = self.cdrs.lock().unwrap();
+        let before = cdrs.len();
+        cdrs.retain(|c| c.start_time >= cutoff);
+        Ok((before - cdrs.len()) as i64)
+    }
+
+    async fn list_for_user(
+        &self,
+        username: &str,
+        limit: i64,
+    ) -> Result<Vec<CallDetailRecord>, String> {
+        let cdrs = self.cdrs.lock().unwrap();
+        let involved = |c: &CallDetailRecord| c.caller_username == username || c.callee_username == username;
+        let correlations: Vec<&str> = cdrs
+            .iter()
+            .filter(|c| involved(c))
+            .filter_map(|c| c.correlation_id.as_deref())
+            .collect();
+        let mut listed: Vec<CallDetailRecord> = cdrs
+            .iter()
+            .filter(|c| {
+                involved(c)
+                    || c.correlation_id
+                        .as_deref()
+                        .is_some_and(|id| correlations.contains(&id))
+            })
+            .cloned()
+            .collect();
+        listed.sort_by_key(|c| std::cmp::Reverse(c.start_time));
+        listed.truncate(limit.max(0) as usize);
+        Ok(listed)
+    }
+}
+
+#[async_trait]
+impl SubjectStore for MemoryCdrRepository {
+    fn name(&self) -> &'static str {
+        "cdrs"
+    }
+
+    fn subject_fields(&self) -> &'static [&'static str] {
+        CallDetailRecord::SUBJECT_FIELDS
+    }
+
+    async fn find_subject(&self, subject: &ErasureSubject) -> Result<Vec<SubjectReference>, String> {
+        let cdrs = self.cdrs.lock().unwrap();
+        Ok(cdrs
+            .iter()
+            .filter_map(|c| SubjectReference::to(c, c.id, c.start_time, subject))
+            .collect())
+    }
+
+    async fn pseudonymize(
+        &self,
+        subject: &ErasureSubject,
+        pseudonyms: &Pseudonymizer,
+        records: &[SubjectReference],
+    ) -> Result<u64, String> {
+        let mut cdrs = self.cdrs.lock().unwrap();
+        let mut count = 0;
+        for cdr in cdrs
+            .iter_mut()
+            .filter(|c| records.iter().any(|r| r.record_id == c.id.to_string()))
+        {
+            if cdr.pseudonymize(subject, pseudonyms) {
+                cdr.updated_at = Utc::now();
+                count += 1;
+            }
+        }
+        Ok(count)
+    }
+}
@@ -0,0 +1,64 @@
+//! In-memory DeviceEnrollmentRepository
+//!
+//! Used in tests; enrollments and device credentials are lost on restart.
+
+use crate::domain::device_enrollment::{DeviceCredential, DeviceEnrollmentRepository, Enrollment};
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use std::sync::Mutex;
+use uuid::Uuid;
+
+#[derive(Default)]
+pub struct MemoryDeviceEnrollmentRepository {
+    enrollments: Mutex<Vec<Enrollment>>,
+    credentials: Mutex<Vec<DeviceCredential>>,
+}
+
+impl MemoryDeviceEnrollmentRepository {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+#[async_trait]
+impl DeviceEnrollmentRepository for MemoryDeviceEnrollmentRepository {
+    async fn create_enrollment(&self, enrollment: &Enrollment) -> Result<(), String> {
+        self.enrollments.lock().unwrap().push(enrollment.clone());
+        Ok(())
+    }
+
+    async fn redeem(&self, token_hash: &str, at: DateTime<Utc>) -> Result<Option<Enrollment>, String> {
+        let mut enrollments = self.enrollments.lock().unwrap();
+        Ok(enrollments
+            .iter_mut()
+            .find(|e| e.token_hash == token_hash && e.is_redeemable(at))
+            .map(|e| {
+                e.uses += 1;
+                e.clone()
+            }))
+    }
+
+    async fn create_credential(&self, credential: &DeviceCredential) -> Result<(), String> {
+        self.credentials.lock().unwrap().push(credential.clone());
+        Ok(())
+    }
+
+    async fn list_credentials(&self, user_id: i32) -> Result<Vec<DeviceCredential>, String> {
+        let credentials = self.credentials.lock().unwrap();
+        Ok(credentials.iter().filter(|c| c.user_id == user_id).cloned().collect())
+    }
+
+    async fn revoke_credential(&self, user_id: i32, id: Uuid, at: DateTime<Utc>) -> Result<bool, String> {
+        let mut credentials = self.credentials.lock().unwrap();
+        match credentials
+            .iter_mut()
+            .find(|c| c.id == id && c.user_id == user_id && c.is_active())
+        {
+            Some(credential) => {
+                credential.revoked_at = Some(at);
+                Ok(true)
+            }
+            None => Ok(false),
+        }
+    }
+}
@@ -0,0 +1,225 @@
+//! In-memory MessageRepository
+//!
+//! Used when the server runs without a database; messages are lost on
+//! restart.
+
+use crate::domain::instant_messaging::{
+    InstantMessage, MessageFilter, MessageRepository, MessageStatus,
+};
+use crate::domain::privacy::{
+    ErasureSubject, Pseudonymizer, SubjectBearing, SubjectReference, SubjectStore,
+};
+use async_trait::async_trait;
+use chrono::Utc;
+use std::collections::HashMap;
+use std::sync::Mutex;
+use uuid::Uuid;
+
+/// Messages kept in insertion (send) order
+#[derive(Default)]
+pub struct MemoryMessageRepository {
+    messages: Mutex<Vec<InstantMessage>>,
+}
+
+impl MemoryMessageRepository {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+fn is_unread(message: &InstantMessage) -> bool {
+    matches!(
+        message.status,
+        MessageStatus::Pending | MessageStatus::Delivered
+    )
+}
+
+#[async_trait]
+impl MessageRepository for MemoryMessageRepository {
+    async fn save(&self, message: &InstantMessage) -> Result<(), String> {
+        self.messages.lock().unwrap().push(message.clone());
+        Ok(())
+    }
+
+    async fn update(&self, message: &InstantMessage) -> Result<(), String> {
+        let mut messages = self.messages.lock().unwrap();
+        match messages.iter_mut().find(|m| m.id == message.id) {
+            Some(stored) => {
+                *stored = message.clone();
+                Ok(())
+            }
+            None => Err(format!("Message not found: {}", message.id)),
+        }
+    }
+
+    async fn get_by_id(&self, id: Uuid) -> Result<Option<InstantMessage>, String> {
+        let messages = self.messages.lock().unwrap();
+        Ok(messages.iter().find(|m| m.id == id).cloned())
+    }
+
+    async fn list_pending_for(&self, recipient: &str) -> Result<Vec<InstantMessage>, String> {
+        let messages = self.messages.lock().unwrap();
+        Ok(messages
+            .iter()
+            .filter(|m| m.to == recipient && m.status == MessageStatus::Pending)
+            .cloned()
+            .collect())
+    }
+
+    async fn list_pending(&self) -> Result<Vec<InstantMessage>, String> {
+        let messages = self.messages.lock().unwrap();
+        Ok(messages
+            .iter()
+            .filter(|m| m.status == MessageStatus::Pending)
+            .cloned()
+            .collect())
+    }
+
+    async fn history(
+        &self,
+        user: &str,
+        filter: &MessageFilter,
+        limit: usize,
+    ) -> Result<Vec<InstantMessage>, String> {
+        let messages = self.messages.lock().unwrap();
+        let mut history: Vec<InstantMessage> = messages
+            .iter()
+            .filter(|m| filter.matches(user, m))
+            .cloned()
+            .collect();
+        history.sort_by_key(|m| std::cmp::Reverse(m.timestamp));
+        history.truncate(limit);
+        Ok(history)
+    }
+
+    async fn unread_counts(&self, user: &str) -> Result<HashMap<String, u64>, String> {
+        let messages = self.messages.lock().unwrap();
+        let mut counts = HashMap::new();
+        for message in messages.iter().filter(|m| m.to == user && is_unread(m)) {
+            *counts.entry(message.from.clone()).or_insert(0) += 1;
+        }
+        Ok(counts)
+    }
+
+    async fn mark_read(&self, user: &str, peer: &str) -> Result<u64, String> {
+        let mut messages = self.messages.lock().unwrap();
+        let mut count = 0;
+        for message in messages
+            .iter_mut()
+            .filter(|m| m.to == user && m.from == peer && is_unread(m))
+        {
+            message.status = MessageStatus::Read;
+            message.read_at = Some(Utc::now());
+            count += 1;
+        }
+        Ok(count)
+    }
+
+    async fn delete_history(&self, user: &str, filter: &MessageFilter) -> Result<u64, String> {
+        let mut messages = self.messages.lock().unwrap();
+        let before = messages.len();
+        messages.retain(|m| !filter.matches(user, m));
+        Ok((before - messages.len()) as u64)
+    }
+}
+
+#[async_trait]
+impl SubjectStore for MemoryMessageRepository {
+    fn name(&self) -> &'static str {
+        "messages"
+    }
+
+    fn subject_fields(&self) -> &'static [&'static str] {
+        InstantMessage::SUBJECT_FIELDS
+    }
+
+    async fn find_subject(&self, subject: &ErasureSubject) -> Result<Vec<SubjectReference>, String> {
+        let messages = self.messages.lock().unwrap();
+        Ok(messages
+            .iter()
+            .filter_map(|m| SubjectReference::to(m, m.id, m.timestamp, subject))
+            .collect())
+    }
+
+    async fn pseudonymize(
+        &self,
+        subject: &ErasureSubject,
+        pseudonyms: &Pseudonymizer,
+        records: &[SubjectReference],
+    ) -> Result<u64, String> {
+        let mut messages = self.messages.lock().unwrap();
+        let mut count = 0;
+        for message in messages
+            .iter_mut()
+            .filter(|m| records.iter().any(|r| r.record_id == m.id.to_string()))
+        {
+            if message.pseudonymize(subject, pseudonyms) {
+                count += 1;
+            }
+        }
+        Ok(count)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::Duration;
+
+    #[tokio::test]
+    async fn test_history_pages_newest_first() {
+        let repository = MemoryMessageRepository::new();
+        let start = Utc::now() - Duration::minutes(10);
+        for i in 0..5 {
+            let (from, to) = if i % 2 == 0 {
+                ("alice", "bob")
+            } else {
+                ("bob", "alice")
+            };
+            let mut message =
+                InstantMessage::text(from.to_string(), to.to_string(), format!("{}", i));
+            message.timestamp = start + Duration::minutes(i);
+            repository.save(&message).await.unwrap();
+        }
+        let other =
+            InstantMessage::text("carol".to_string(), "alice".to_string(), "hi".to_string());
+        repository.save(&other).await.unwrap();
+
+        let mut filter = MessageFilter {
+            peer: Some("bob".to_string()),
+            before: None,
+        };
+        let page = repository.history("alice", &filter, 2).await.unwrap();
+        let bodies: Vec<String> = page
+            .iter()
+            .map(|m| m.content_as_string().unwrap())
+            .collect();
+        assert_eq!(bodies, vec!["4", "3"]);
+
+        filter.before = Some(page[1].timestamp);
+        let page = repository.history("alice", &filter, 2).await.unwrap();
+        let bodies: Vec<String> = page
+            .iter()
+            .map(|m| m.content_as_string().unwrap())
+            .collect();
+        assert_eq!(bodies, vec!["2", "1"]);
+
+        assert_eq!(
+            repository.unread_counts("alice").await.unwrap().get("bob"),
+            Some(&2)
+        );
+        assert_eq!(repository.mark_read("alice", "bob").await.unwrap(), 2);
+        assert_eq!(
+            repository
+                .unread_counts("alice")
+                .await
+                .unwrap()
+                .get("carol"),
+            Some(&1)
+        );
+        assert_eq!(
+            repository.delete_history("alice", &filter).await.unwrap(),
+            3
+        );
+    }
+}
@@ -1,3 +1,21 @@
 //! In-memory repository implementations for testing
 
-// Placeholder for in-memory implementations
+pub mod announcement_route_repository;
+pub mod auto_attendant_repository;
+pub mod call_repository;
+pub mod cdr_repository;
+pub mod device_enrollment_repository;
+pub mod message_repository;
+pub mod scheduled_call_repository;
+pub mod voicemail_list_repository;
+pub mod voicemail_repository;
+
+pub use announcement_route_repository::MemoryAnnouncementRouteRepository;
+pub use auto_attendant_repository::MemoryAutoAttendantRepository;
+pub use call_repository::MemoryCallRepository;
+pub use cdr_repository::MemoryCdrRepository;
+pub use device_enrollment_repository::MemoryDeviceEnrollmentRepository;
+pub use message_repository::MemoryMessageRepository;
+pub use scheduled_call_repository::MemoryScheduledCallRepository;
+pub use voicemail_list_repository::MemoryVoicemailListRepository;
+pub use voicemail_repository::MemoryVoicemailRepository;
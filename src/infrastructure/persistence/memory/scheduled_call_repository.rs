@@ -0,0 +1,69 @@
+//! In-memory ScheduledCallRepository
+//!
+//! Used in tests; schedules are lost on restart.
+
+use crate::domain::scheduled_call::{ScheduledCall, ScheduledCallRepository};
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use std::sync::Mutex;
+use uuid::Uuid;
+
+#[derive(Default)]
+pub struct MemoryScheduledCallRepository {
+    calls: Mutex<Vec<ScheduledCall>>,
+}
+
+impl MemoryScheduledCallRepository {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+#[async_trait]
+impl ScheduledCallRepository for MemoryScheduledCallRepository {
+    async fn create(&self, call: &ScheduledCall) -> Result<(), String> {
+        self.calls.lock().unwrap().push(call.clone());
+        Ok(())
+    }
+
+    async fn get(&self, id: Uuid) -> Result<Option<ScheduledCall>, String> {
+        Ok(self.calls.lock().unwrap().iter().find(|c| c.id == id).cloned())
+    }
+
+    async fn list(&self) -> Result<Vec<ScheduledCall>, String> {
+        let mut calls = self.calls.lock().unwrap().clone();
+        calls.sort_by_key(|c| c.next_attempt_at);
+        Ok(calls)
+    }
+
+    async fn list_due(&self, now: DateTime<Utc>) -> Result<Vec<ScheduledCall>, String> {
+        let mut calls: Vec<ScheduledCall> = self
+            .calls
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|c| c.is_due(now))
+            .cloned()
+            .collect();
+        calls.sort_by_key(|c| c.next_attempt_at);
+        Ok(calls)
+    }
+
+    async fn update(&self, call: &ScheduledCall) -> Result<bool, String> {
+        let mut calls = self.calls.lock().unwrap();
+        match calls.iter_mut().find(|c| c.id == call.id) {
+            Some(existing) => {
+                *existing = call.clone();
+                Ok(true)
+            }
+            None => Ok(false),
+        }
+    }
+
+    async fn delete(&self, id: Uuid) -> Result<bool, String> {
+        let mut calls = self.calls.lock().unwrap();
+        let before = calls.len();
+        calls.retain(|c| c.id != id);
+        Ok(calls.len() < before)
+    }
+}
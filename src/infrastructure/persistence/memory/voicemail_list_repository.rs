@@ -0,0 +1,64 @@
+//! In-memory VoicemailListRepository
+//!
+//! Used when the server runs without a database; lists are lost on
+//! restart.
+
+use crate::domain::voicemail_list::{VoicemailDistributionList, VoicemailListRepository};
+use async_trait::async_trait;
+use std::sync::Mutex;
+use uuid::Uuid;
+
+/// Lists kept in creation order
+#[derive(Default)]
+pub struct MemoryVoicemailListRepository {
+    lists: Mutex<Vec<VoicemailDistributionList>>,
+}
+
+impl MemoryVoicemailListRepository {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+#[async_trait]
+impl VoicemailListRepository for MemoryVoicemailListRepository {
+    async fn create(&self, list: &VoicemailDistributionList) -> Result<(), String> {
+        self.lists.lock().unwrap().push(list.clone());
+        Ok(())
+    }
+
+    async fn get_by_id(&self, id: Uuid) -> Result<Option<VoicemailDistributionList>, String> {
+        let lists = self.lists.lock().unwrap();
+        Ok(lists.iter().find(|l| l.id == id).cloned())
+    }
+
+    async fn find_by_number(&self, number: &str) -> Result<Option<VoicemailDistributionList>, String> {
+        let lists = self.lists.lock().unwrap();
+        Ok(lists.iter().find(|l| l.number == number).cloned())
+    }
+
+    async fn list(&self) -> Result<Vec<VoicemailDistributionList>, String> {
+        Ok(self.lists.lock().unwrap().clone())
+    }
+
+    async fn update(&self, list: &VoicemailDistributionList) -> Result<(), String> {
+        let mut lists = self.lists.lock().unwrap();
+        match lists.iter_mut().find(|l| l.id == list.id) {
+            Some(stored) => {
+                *stored = list.clone();
+                Ok(())
+            }
+            None => Err(format!("Distribution list not found: {}", list.id)),
+        }
+    }
+
+    async fn delete(&self, id: Uuid) -> Result<(), String> {
+        let mut lists = self.lists.lock().unwrap();
+        let before = lists.len();
+        lists.retain(|l| l.id != id);
+        if lists.len() == before {
+            return Err(format!("Distribution list not found: {}", id));
+        }
+        Ok(())
+    }
+}
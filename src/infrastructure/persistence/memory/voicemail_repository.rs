@@ -0,0 +1,98 @@
+//! In-memory VoicemailRepository
+//!
+//! Used when the server runs without a database; messages and mailboxes
+//! are lost on restart.
+
+use crate::domain::voicemail::{VoicemailMailbox, VoicemailMessage, VoicemailRepository, VoicemailStatus};
+use async_trait::async_trait;
+use std::collections::HashMap;
+use std::sync::Mutex;
+use uuid::Uuid;
+
+/// Messages kept in creation order, mailboxes by id
+#[derive(Default)]
+pub struct MemoryVoicemailRepository {
+    messages: Mutex<Vec<VoicemailMessage>>,
+    mailboxes: Mutex<HashMap<String, VoicemailMailbox>>,
+}
+
+impl MemoryVoicemailRepository {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+#[async_trait]
+impl VoicemailRepository for MemoryVoicemailRepository {
+    async fn create_message(&self, message: VoicemailMessage) -> Result<VoicemailMessage, String> {
+        self.messages.lock().unwrap().push(message.clone());
+        Ok(message)
+    }
+
+    async fn get_message(&self, id: Uuid) -> Result<Option<VoicemailMessage>, String> {
+        let messages = self.messages.lock().unwrap();
+        Ok(messages.iter().find(|m| m.id == id).cloned())
+    }
+
+    async fn list_messages(
+        &self,
+        mailbox_id: &str,
+        status: Option<VoicemailStatus>,
+    ) -> Result<Vec<VoicemailMessage>, String> {
+        let messages = self.messages.lock().unwrap();
+        Ok(messages
+            .iter()
+            .filter(|m| m.mailbox_id == mailbox_id && status.as_ref().is_none_or(|s| &m.status == s))
+            .cloned()
+            .collect())
+    }
+
+    async fn update_message_status(&self, id: Uuid, status: VoicemailStatus) -> Result<(), String> {
+        let mut messages = self.messages.lock().unwrap();
+        match messages.iter_mut().find(|m| m.id == id) {
+            Some(message) => {
+                message.status = status;
+                Ok(())
+            }
+            None => Err(format!("Voicemail message not found: {}", id)),
+        }
+    }
+
+    async fn set_transcript(&self, id: Uuid, transcript: &str, confidence: Option<f32>) -> Result<(), String> {
+        let mut messages = self.messages.lock().unwrap();
+        match messages.iter_mut().find(|m| m.id == id) {
+            Some(message) => {
+                message.transcript = Some(transcript.to_string());
+                message.transcript_confidence = confidence;
+                Ok(())
+            }
+            None => Err(format!("Voicemail message not found: {}", id)),
+        }
+    }
+
+    async fn delete_message(&self, id: Uuid) -> Result<(), String> {
+        self.messages.lock().unwrap().retain(|m| m.id != id);
+        Ok(())
+    }
+
+    async fn count_messages(&self, mailbox_id: &str, status: Option<VoicemailStatus>) -> Result<u32, String> {
+        Ok(self.list_messages(mailbox_id, status).await?.len() as u32)
+    }
+
+    async fn count_audio_references(&self, audio_file_path: &str) -> Result<u32, String> {
+        let messages = self.messages.lock().unwrap();
+        Ok(messages.iter().filter(|m| m.audio_file_path == audio_file_path).count() as u32)
+    }
+
+    async fn get_mailbox(&self, mailbox_id: &str) -> Result<Option<VoicemailMailbox>, String> {
+        Ok(self.mailboxes.lock().unwrap().get(mailbox_id).cloned())
+    }
+
+    async fn save_mailbox(&self, mailbox: VoicemailMailbox) -> Result<VoicemailMailbox, String> {
+        self.mailboxes
+            .lock()
+            .unwrap()
+            .insert(mailbox.mailbox_id.clone(), mailbox.clone());
+        Ok(mailbox)
+    }
+}
@@ -0,0 +1,308 @@
+//! PostgreSQL implementation of MessageRepository
+
+use crate::domain::instant_messaging::{
+    InstantMessage, MessageContentType, MessageFilter, MessageRepository, MessageStatus,
+};
+use crate::domain::privacy::{
+    ErasureSubject, Pseudonymizer, SubjectBearing, SubjectReference, SubjectStore,
+};
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use sqlx::{FromRow, PgPool};
+use std::collections::HashMap;
+use tracing::{debug, error};
+use uuid::Uuid;
+
+const MESSAGE_COLUMNS: &str = "id, sender, recipient, content_type, content, status, sent_at, \
+                               delivered_at, read_at, attempts, next_attempt_at";
+
+#[derive(FromRow)]
+struct MessageRow {
+    id: Uuid,
+    sender: String,
+    recipient: String,
+    content_type: String,
+    content: Vec<u8>,
+    status: String,
+    sent_at: DateTime<Utc>,
+    delivered_at: Option<DateTime<Utc>>,
+    read_at: Option<DateTime<Utc>>,
+    attempts: i32,
+    next_attempt_at: Option<DateTime<Utc>>,
+}
+
+impl From<MessageRow> for InstantMessage {
+    fn from(r: MessageRow) -> Self {
+        InstantMessage {
+            id: r.id,
+            from: r.sender,
+            to: r.recipient,
+            content_type: MessageContentType::from_str(&r.content_type),
+            content: r.content,
+            status: r.status.parse().unwrap_or(MessageStatus::Pending),
+            timestamp: r.sent_at,
+            delivered_at: r.delivered_at,
+            read_at: r.read_at,
+            group_id: None,
+            attempts: r.attempts.max(0) as u32,
+            next_attempt_at: r.next_attempt_at,
+        }
+    }
+}
+
+fn db_error(context: &str, e: sqlx::Error) -> String {
+    error!("{}: {}", context, e);
+    format!("Database error: {}", e)
+}
+
+pub struct PgMessageRepository {
+    pool: PgPool,
+}
+
+impl PgMessageRepository {
+    pub fn new(pool: PgPool) -> Self {
+        Self { pool }
+    }
+}
+
+#[async_trait]
+impl MessageRepository for PgMessageRepository {
+    async fn save(&self, message: &InstantMessage) -> Result<(), String> {
+        debug!("Storing message {} from {} to {}", message.id, message.from, message.to);
+
+        sqlx::query(
+            r#"
+            INSERT INTO instant_messages
+                (id, sender, recipient, content_type, content, status, sent_at,
+                 delivered_at, read_at, attempts, next_attempt_at)
+            VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11)
+            "#,
+        )
+        .bind(message.id)
+        .bind(&message.from)
+        .bind(&message.to)
+        .bind(message.content_type.to_string())
+        .bind(&message.content)
+        .bind(message.status.as_str())
+        .bind(message.timestamp)
+        .bind(message.delivered_at)
+        .bind(message.read_at)
+        .bind(message.attempts as i32)
+        .bind(message.next_attempt_at)
+        .execute(&self.pool)
+        .await
+        .map_err(|e| db_error("Failed to store message", e))?;
+
+        Ok(())
+    }
+
+    async fn update(&self, message: &InstantMessage) -> Result<(), String> {
+        let result = sqlx::query(
+            r#"
+            UPDATE instant_messages
+            SET status = $2, delivered_at = $3, read_at = $4, attempts = $5, next_attempt_at = $6
+            WHERE id = $1
+            "#,
+        )
+        .bind(message.id)
+        .bind(message.status.as_str())
+        .bind(message.delivered_at)
+        .bind(message.read_at)
+        .bind(message.attempts as i32)
+        .bind(message.next_attempt_at)
+        .execute(&self.pool)
+        .await
+        .map_err(|e| db_error("Failed to update message", e))?;
+
+        if result.rows_affected() == 0 {
+            return Err(format!("Message not found: {}", message.id));
+        }
+        Ok(())
+    }
+
+    async fn get_by_id(&self, id: Uuid) -> Result<Option<InstantMessage>, String> {
+        sqlx::query_as::<_, MessageRow>(&format!(
+            "SELECT {} FROM instant_messages WHERE id = $1",
+            MESSAGE_COLUMNS
+        ))
+        .bind(id)
+        .fetch_optional(&self.pool)
+        .await
+        .map(|row| row.map(Into::into))
+        .map_err(|e| db_error("Failed to get message", e))
+    }
+
+    async fn list_pending_for(&self, recipient: &str) -> Result<Vec<InstantMessage>, String> {
+        let rows = sqlx::query_as::<_, MessageRow>(&format!(
+            "SELECT {} FROM instant_messages \
+             WHERE recipient = $1 AND status = 'pending' \
+             ORDER BY sent_at",
+            MESSAGE_COLUMNS
+        ))
+        .bind(recipient)
+        .fetch_all(&self.pool)
+        .await
+        .map_err(|e| db_error("Failed to list pending messages", e))?;
+
+        Ok(rows.into_iter().map(Into::into).collect())
+    }
+
+    async fn list_pending(&self) -> Result<Vec<InstantMessage>, String> {
+        let rows = sqlx::query_as::<_, MessageRow>(&format!(
+            "SELECT {} FROM instant_messages WHERE status = 'pending' ORDER BY sent_at",
+            MESSAGE_COLUMNS
+        ))
+        .fetch_all(&self.pool)
+        .await
+        .map_err(|e| db_error("Failed to list pending messages", e))?;
+
+        Ok(rows.into_iter().map(Into::into).collect())
+    }
+
+    async fn history(
+        &self,
+        user: &str,
+        filter: &MessageFilter,
+        limit: usize,
+    ) -> Result<Vec<InstantMessage>, String> {
+        let rows = sqlx::query_as::<_, MessageRow>(&format!(
+            "SELECT {} FROM instant_messages \
+             WHERE (sender = $1 OR recipient = $1) \
+               AND ($2::VARCHAR IS NULL OR sender = $2 OR recipient = $2) \
+               AND ($3::TIMESTAMPTZ IS NULL OR sent_at < $3) \
+             ORDER BY sent_at DESC \
+             LIMIT $4",
+            MESSAGE_COLUMNS
+        ))
+        .bind(user)
+        .bind(filter.peer.as_deref())
+        .bind(filter.before)
+        .bind(limit as i64)
+        .fetch_all(&self.pool)
+        .await
+        .map_err(|e| db_error("Failed to list message history", e))?;
+
+        Ok(rows.into_iter().map(Into::into).collect())
+    }
+
+    async fn unread_counts(&self, user: &str) -> Result<HashMap<String, u64>, String> {
+        let rows: Vec<(String, i64)> = sqlx::query_as(
+            r#"
+            SELECT sender, COUNT(*)
+            FROM instant_messages
+            WHERE recipient = $1 AND status IN ('pending', 'delivered')
+            GROUP BY sender
+            "#,
+        )
+        .bind(user)
+        .fetch_all(&self.pool)
+        .await
+        .map_err(|e| db_error("Failed to count unread messages", e))?;
+
+        Ok(rows
+            .into_iter()
+            .map(|(sender, count)| (sender, count.max(0) as u64))
+            .collect())
+    }
+
+    async fn mark_read(&self, user: &str, peer: &str) -> Result<u64, String> {
+        let result = sqlx::query(
+            r#"
+            UPDATE instant_messages
+            SET status = 'read', read_at = NOW()
+            WHERE recipient = $1 AND sender = $2 AND status IN ('pending', 'delivered')
+            "#,
+        )
+        .bind(user)
+        .bind(peer)
+        .execute(&self.pool)
+        .await
+        .map_err(|e| db_error("Failed to mark messages read", e))?;
+
+        Ok(result.rows_affected())
+    }
+
+    async fn delete_history(&self, user: &str, filter: &MessageFilter) -> Result<u64, String> {
+        let result = sqlx::query(
+            r#"
+            DELETE FROM instant_messages
+            WHERE (sender = $1 OR recipient = $1)
+              AND ($2::VARCHAR IS NULL OR sender = $2 OR recipient = $2)
+              AND ($3::TIMESTAMPTZ IS NULL OR sent_at < $3)
+            "#,
+        )
+        .bind(user)
+        .bind(filter.peer.as_deref())
+        .bind(filter.before)
+        .execute(&self.pool)
+        .await
+        .map_err(|e| db_error("Failed to delete messages", e))?;
+
+        Ok(result.rows_affected())
+    }
+}
+
+#[async_trait]
+impl SubjectStore for PgMessageRepository {
+    fn name(&self) -> &'static str {
+        "messages"
+    }
+
+    fn subject_fields(&self) -> &'static [&'static str] {
+        InstantMessage::SUBJECT_FIELDS
+    }
+
+    async fn find_subject(&self, subject: &ErasureSubject) -> Result<Vec<SubjectReference>, String> {
+        let rows: Vec<MessageRow> = sqlx::query_as(&format!(
+            "SELECT {} FROM instant_messages WHERE sender ILIKE ANY($1) OR recipient ILIKE ANY($1)",
+            MESSAGE_COLUMNS
+        ))
+        .bind(subject.search_patterns())
+        .fetch_all(&self.pool)
+        .await
+        .map_err(|e| db_error("Failed to search messages for an erasure subject", e))?;
+
+        Ok(rows
+            .into_iter()
+            .map(InstantMessage::from)
+            .filter_map(|m| SubjectReference::to(&m, m.id, m.timestamp, subject))
+            .collect())
+    }
+
+    async fn pseudonymize(
+        &self,
+        subject: &ErasureSubject,
+        pseudonyms: &Pseudonymizer,
+        records: &[SubjectReference],
+    ) -> Result<u64, String> {
+        let ids: Vec<Uuid> = records
+            .iter()
+            .filter_map(|r| r.record_id.parse().ok())
+            .collect();
+        let rows: Vec<MessageRow> = sqlx::query_as(&format!(
+            "SELECT {} FROM instant_messages WHERE id = ANY($1)",
+            MESSAGE_COLUMNS
+        ))
+        .bind(&ids)
+        .fetch_all(&self.pool)
+        .await
+        .map_err(|e| db_error("Failed to load messages to pseudonymize", e))?;
+
+        let mut count = 0;
+        for mut message in rows.into_iter().map(InstantMessage::from) {
+            if !message.pseudonymize(subject, pseudonyms) {
+                continue;
+            }
+            sqlx::query("UPDATE instant_messages SET sender = $2, recipient = $3 WHERE id = $1")
+                .bind(message.id)
+                .bind(&message.from)
+                .bind(&message.to)
+                .execute(&self.pool)
+                .await
+                .map_err(|e| db_error("Failed to pseudonymize message", e))?;
+            count += 1;
+        }
+        debug!("Pseudonymized {} messages", count);
+        Ok(count)
+    }
+}
@@ -1,6 +1,10 @@
 //! Persistence implementations
 
+pub mod caching_user_repository;
+pub mod cdr_journal;
+pub mod health;
 pub mod memory;
+pub mod resilient_cdr_repository;
 #[cfg(feature = "postgres")]
 pub mod postgres;
 #[cfg(feature = "postgres")]
@@ -21,7 +25,36 @@ pub mod call_queue_repository;
 pub mod tenant_repository;
 #[cfg(feature = "postgres")]
 pub mod sip_trunk_repository;
+#[cfg(feature = "postgres")]
+pub mod speed_dial_repository;
+#[cfg(feature = "postgres")]
+pub mod queue_event_repository;
+#[cfg(feature = "postgres")]
+pub mod message_repository;
+#[cfg(feature = "postgres")]
+pub mod announcement_route_repository;
+#[cfg(feature = "postgres")]
+pub mod voicemail_list_repository;
+#[cfg(feature = "postgres")]
+pub mod auto_attendant_repository;
+#[cfg(feature = "postgres")]
+pub mod call_repository;
+#[cfg(feature = "postgres")]
+pub mod idempotency_repository;
+#[cfg(feature = "postgres")]
+pub mod device_enrollment_repository;
+#[cfg(feature = "postgres")]
+pub mod scheduled_call_repository;
 
+pub use caching_user_repository::CachingUserRepository;
+pub use cdr_journal::CdrJournal;
+pub use health::{DbHealth, DbHealthConfig, DbMode};
+pub use memory::{
+    MemoryAnnouncementRouteRepository, MemoryAutoAttendantRepository, MemoryCallRepository,
+    MemoryCdrRepository, MemoryDeviceEnrollmentRepository, MemoryMessageRepository,
+    MemoryScheduledCallRepository, MemoryVoicemailListRepository, MemoryVoicemailRepository,
+};
+pub use resilient_cdr_repository::ResilientCdrRepository;
 #[cfg(feature = "postgres")]
 pub use database::{create_pool, run_migrations, DatabaseConfig};
 #[cfg(feature = "postgres")]
@@ -40,3 +73,23 @@ pub use call_queue_repository::PgCallQueueRepository;
 pub use tenant_repository::PgTenantRepository;
 #[cfg(feature = "postgres")]
 pub use sip_trunk_repository::PgSipTrunkRepository;
+#[cfg(feature = "postgres")]
+pub use speed_dial_repository::PgSpeedDialRepository;
+#[cfg(feature = "postgres")]
+pub use queue_event_repository::PgQueueEventRepository;
+#[cfg(feature = "postgres")]
+pub use message_repository::PgMessageRepository;
+#[cfg(feature = "postgres")]
+pub use announcement_route_repository::PgAnnouncementRouteRepository;
+#[cfg(feature = "postgres")]
+pub use voicemail_list_repository::PgVoicemailListRepository;
+#[cfg(feature = "postgres")]
+pub use auto_attendant_repository::PgAutoAttendantRepository;
+#[cfg(feature = "postgres")]
+pub use call_repository::PgCallRepository;
+#[cfg(feature = "postgres")]
+pub use idempotency_repository::PgIdempotencyRepository;
+#[cfg(feature = "postgres")]
+pub use device_enrollment_repository::PgDeviceEnrollmentRepository;
+#[cfg(feature = "postgres")]
+pub use scheduled_call_repository::PgScheduledCallRepository;
@@ -0,0 +1,138 @@
+//! PostgreSQL implementation of QueueEventRepository
+
+use crate::domain::queue_reporting::{QueueEvent, QueueEventRepository, QueueEventType};
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use sqlx::{FromRow, PgPool};
+use tracing::{error, warn};
+use uuid::Uuid;
+
+#[derive(FromRow)]
+struct QueueEventRow {
+    id: Uuid,
+    queue_id: Uuid,
+    call_id: String,
+    agent_id: Option<i32>,
+    event_type: String,
+    occurred_at: DateTime<Utc>,
+    wait_secs: i64,
+    talk_secs: i64,
+    wrap_up_secs: i64,
+}
+
+impl QueueEventRow {
+    fn into_event(self) -> Option<QueueEvent> {
+        let event_type = match self.event_type.parse::<QueueEventType>() {
+            Ok(event_type) => event_type,
+            Err(_) => {
+                warn!("Skipping queue event {} with unknown type {}", self.id, self.event_type);
+                return None;
+            }
+        };
+        Some(QueueEvent {
+            id: self.id,
+            queue_id: self.queue_id,
+            call_id: self.call_id,
+            agent_id: self.agent_id,
+            event_type,
+            occurred_at: self.occurred_at,
+            wait_secs: self.wait_secs,
+            talk_secs: self.talk_secs,
+            wrap_up_secs: self.wrap_up_secs,
+        })
+    }
+}
+
+pub struct PgQueueEventRepository {
+    pool: PgPool,
+}
+
+impl PgQueueEventRepository {
+    pub fn new(pool: PgPool) -> Self {
+        Self { pool }
+    }
+}
+
+#[async_trait]
+impl QueueEventRepository for PgQueueEventRepository {
+    async fn record(&self, event: &QueueEvent) -> Result<(), String> {
+        sqlx::query(
+            r#"
+            INSERT INTO queue_events
+            (id, queue_id, call_id, agent_id, event_type, occurred_at, wait_secs, talk_secs, wrap_up_secs)
+            VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9)
+            "#,
+        )
+        .bind(event.id)
+        .bind(event.queue_id)
+        .bind(&event.call_id)
+        .bind(event.agent_id)
+        .bind(event.event_type.as_str())
+        .bind(event.occurred_at)
+        .bind(event.wait_secs)
+        .bind(event.talk_secs)
+        .bind(event.wrap_up_secs)
+        .execute(&self.pool)
+        .await
+        .map_err(|e| {
+            error!("Failed to record queue event: {}", e);
+            format!("Database error: {}", e)
+        })?;
+
+        Ok(())
+    }
+
+    async fn list_for_queue(
+        &self,
+        queue_id: Uuid,
+        from: DateTime<Utc>,
+        to: DateTime<Utc>,
+    ) -> Result<Vec<QueueEvent>, String> {
+        let rows = sqlx::query_as::<_, QueueEventRow>(
+            r#"
+            SELECT id, queue_id, call_id, agent_id, event_type, occurred_at, wait_secs, talk_secs, wrap_up_secs
+            FROM queue_events
+            WHERE queue_id = $1 AND occurred_at >= $2 AND occurred_at < $3
+            ORDER BY occurred_at
+            "#,
+        )
+        .bind(queue_id)
+        .bind(from)
+        .bind(to)
+        .fetch_all(&self.pool)
+        .await
+        .map_err(|e| {
+            error!("Failed to list events for queue {}: {}", queue_id, e);
+            format!("Database error: {}", e)
+        })?;
+
+        Ok(rows.into_iter().filter_map(QueueEventRow::into_event).collect())
+    }
+
+    async fn list_for_agent(
+        &self,
+        agent_id: i32,
+        from: DateTime<Utc>,
+        to: DateTime<Utc>,
+    ) -> Result<Vec<QueueEvent>, String> {
+        let rows = sqlx::query_as::<_, QueueEventRow>(
+            r#"
+            SELECT id, queue_id, call_id, agent_id, event_type, occurred_at, wait_secs, talk_secs, wrap_up_secs
+            FROM queue_events
+            WHERE agent_id = $1 AND occurred_at >= $2 AND occurred_at < $3
+            ORDER BY occurred_at
+            "#,
+        )
+        .bind(agent_id)
+        .bind(from)
+        .bind(to)
+        .fetch_all(&self.pool)
+        .await
+        .map_err(|e| {
+            error!("Failed to list queue events for agent {}: {}", agent_id, e);
+            format!("Database error: {}", e)
+        })?;
+
+        Ok(rows.into_iter().filter_map(QueueEventRow::into_event).collect())
+    }
+}
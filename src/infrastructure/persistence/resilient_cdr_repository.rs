@@ -0,0 +1,386 @@
+//! CDR repository wrapper that queues writes while the database is degraded
+//!
+//! CDR creates and updates issued during a failover are appended to a
+//! bounded [`CdrJournal`] and replayed in order once the pool recovers; with
+//! an on-disk journal they also survive a restart. Reads by id or Call-ID are
+//! answered from the journal first so pending records stay visible through
+//! the API, and CDR lists and counts are cached as they are read so the same
+//! queries keep being answered while the database is unreachable.
+
+use super::cdr_journal::{CdrJournal, WriteKind};
+use super::health::{classify_db_error, DbErrorClass, DbHealth};
+use crate::domain::cdr::{CallDetailRecord, CdrFilters, CdrRepository};
+use async_trait::async_trait;
+use metrics::{counter, gauge};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use tracing::{error, info, warn};
+use uuid::Uuid;
+
+/// Default number of CDR writes held while degraded
+pub const DEFAULT_CDR_QUEUE_CAPACITY: usize = 10_000;
+
+/// Distinct CDR list and count queries whose last result is kept
+const READ_CACHE_CAPACITY: usize = 256;
+
+/// Last results of CDR queries, keyed by the query
+#[derive(Default)]
+struct ReadCache {
+    lists: HashMap<String, Vec<CallDetailRecord>>,
+    counts: HashMap<String, i64>,
+}
+
+/// CDR repository that survives transient database outages
+pub struct ResilientCdrRepository {
+    inner: Arc<dyn CdrRepository>,
+    health: Arc<DbHealth>,
+    journal: Mutex<CdrJournal>,
+    capacity: usize,
+    reads: Mutex<ReadCache>,
+}
+
+impl ResilientCdrRepository {
+    /// Wrap a repository with the default queue capacity
+    pub fn new(inner: Arc<dyn CdrRepository>, health: Arc<DbHealth>) -> Self {
+        Self::with_capacity(inner, health, DEFAULT_CDR_QUEUE_CAPACITY)
+    }
+
+    /// Wrap a repository with an explicit queue capacity
+    pub fn with_capacity(
+        inner: Arc<dyn CdrRepository>,
+        health: Arc<DbHealth>,
+        capacity: usize,
+    ) -> Self {
+        Self {
+            inner,
+            health,
+            journal: Mutex::new(CdrJournal::in_memory()),
+            capacity: capacity.max(1),
+            reads: Mutex::new(ReadCache::default()),
+        }
+    }
+
+    /// Queue writes in `journal` (e.g. one opened on disk) instead of in
+    /// memory; the writes it recovered are flushed by the next [`flush`](Self::flush)
+    pub fn with_journal(mut self, journal: CdrJournal) -> Self {
+        gauge!("cdr_queue_pending").set(journal.len() as f64);
+        self.journal = Mutex::new(journal);
+        self
+    }
+
+    /// Number of writes waiting to be flushed
+    pub fn pending(&self) -> usize {
+        self.journal.lock().unwrap().len()
+    }
+
+    /// Replay queued writes in order
+    ///
+    /// Stops at the first transient failure so ordering is preserved for the
+    /// next attempt. Writes rejected permanently are logged and dropped.
+    /// Returns the number of writes applied.
+    pub async fn flush(&self) -> usize {
+        let mut applied = 0;
+
+        loop {
+            let next = self.journal.lock().unwrap().front();
+            let Some(write) = next else { break };
+
+            let result = match write.kind {
+                WriteKind::Create => self.inner.create(&write.cdr).await,
+                WriteKind::Update => self.inner.update(&write.cdr).await,
+            };
+
+            match result {
+                Ok(()) => applied += 1,
+                Err(e) => match classify_db_error(&e) {
+                    DbErrorClass::Transient => {
+                        self.health.report_transient(&e);
+                        break;
+                    }
+                    DbErrorClass::Permanent => {
+                        error!("Dropping queued CDR {} after permanent error: {}", write.cdr.id, e);
+                        counter!("cdr_queue_dropped_total").increment(1);
+                    }
+                },
+            }
+            self.journal.lock().unwrap().complete(&write);
+        }
+
+        gauge!("cdr_queue_pending").set(self.pending() as f64);
+        if applied > 0 {
+            info!("Flushed {} queued CDR writes", applied);
+        }
+        applied
+    }
+
+    fn enqueue(&self, kind: WriteKind, cdr: &CallDetailRecord) {
+        let mut journal = self.journal.lock().unwrap();
+        if let Some(dropped) = journal.push(kind, cdr, self.capacity) {
+            warn!("CDR queue full, dropping oldest record {}", dropped);
+            counter!("cdr_queue_dropped_total").increment(1);
+        }
+        gauge!("cdr_queue_pending").set(journal.len() as f64);
+    }
+
+    fn queued_by<F>(&self, pred: F) -> Option<CallDetailRecord>
+    where
+        F: Fn(&CallDetailRecord) -> bool,
+    {
+        self.journal.lock().unwrap().find(pred)
+    }
+
+    async fn write(&self, kind: WriteKind, cdr: &CallDetailRecord) -> Result<(), String> {
+        // Keep ordering: once anything is queued, later writes queue behind it
+        if self.health.is_degraded() || self.pending() > 0 {
+            self.enqueue(kind, cdr);
+            return Ok(());
+        }
+
+        let result = match kind {
+            WriteKind::Create => self.inner.create(cdr).await,
+            WriteKind::Update => self.inner.update(cdr).await,
+        };
+
+        match result {
+            Err(e) if classify_db_error(&e) == DbErrorClass::Transient => {
+                self.health.report_transient(&e);
+                self.enqueue(kind, cdr);
+                Ok(())
+            }
+            other => other,
+        }
+    }
+
+    fn observe<T>(&self, result: Result<T, String>) -> Result<T, String> {
+        if let Err(e) = &result {
+            if classify_db_error(e) == DbErrorClass::Transient {
+                self.health.report_transient(e);
+            }
+        }
+        result
+    }
+
+    /// Remember a successful read, or answer a failed one from the last
+    /// result of the same query while the failure is transient
+    fn read_through<T: Clone>(
+        &self,
+        result: Result<T, String>,
+        key: String,
+        cache: impl Fn(&mut ReadCache) -> &mut HashMap<String, T>,
+    ) -> Result<T, String> {
+        let mut reads = self.reads.lock().unwrap();
+        let entries = cache(&mut reads);
+        match self.observe(result) {
+            Ok(value) => {
+                if !entries.contains_key(&key) && entries.len() >= READ_CACHE_CAPACITY {
+                    entries.clear();
+                }
+                entries.insert(key, value.clone());
+                Ok(value)
+            }
+            Err(e) if classify_db_error(&e) == DbErrorClass::Transient => {
+                entries.get(&key).cloned().ok_or(e)
+            }
+            Err(e) => Err(e),
+        }
+    }
+}
+
+#[async_trait]
+impl CdrRepository for ResilientCdrRepository {
+    async fn create(&self, cdr: &CallDetailRecord) -> Result<(), String> {
+        self.write(WriteKind::Create, cdr).await
+    }
+
+    async fn update(&self, cdr: &CallDetailRecord) -> Result<(), String> {
+        self.write(WriteKind::Update, cdr).await
+    }
+
+    async fn get_by_id(&self, id: Uuid) -> Result<Option<CallDetailRecord>, String> {
+        if let Some(cdr) = self.queued_by(|c| c.id == id) {
+            return Ok(Some(cdr));
+        }
+        let result = self.inner.get_by_id(id).await;
+        self.observe(result)
+    }
+
+    async fn get_by_call_id(&self, call_id: &str) -> Result<Option<CallDetailRecord>, String> {
+        if let Some(cdr) = self.queued_by(|c| c.call_id == call_id) {
+            return Ok(Some(cdr));
+        }
+        let result = self.inner.get_by_call_id(call_id).await;
+        self.observe(result)
+    }
+
+    async fn list(
+        &self,
+        filters: CdrFilters,
+        limit: i64,
+        offset: i64,
+    ) -> Result<Vec<CallDetailRecord>, String> {
+        let key = format!("{:?}/{}/{}", filters, limit, offset);
+        let result = self.inner.list(filters, limit, offset).await;
+        self.read_through(result, key, |reads| &mut reads.lists)
+    }
+
+    async fn count(&self, filters: CdrFilters) -> Result<i64, String> {
+        let key = format!("{:?}", filters);
+        let result = self.inner.count(filters).await;
+        self.read_through(result, key, |reads| &mut reads.counts)
+    }
+
+    async fn delete_older_than(&self, days: i32) -> Result<i64, String> {
+        let result = self.inner.delete_older_than(days).await;
+        self.observe(result)
+    }
+
+    async fn list_for_user(
+        &self,
+        username: &str,
+        limit: i64,
+    ) -> Result<Vec<CallDetailRecord>, String> {
+        let result = self.inner.list_for_user(username, limit).await;
+        self.observe(result)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::domain::cdr::{CallDirection, CallStatus, MockCdrRepository};
+
+    fn cdr(call_id: &str) -> CallDetailRecord {
+        CallDetailRecord::new(
+            call_id.to_string(),
+            "alice".to_string(),
+            "sip:alice@localhost".to_string(),
+            "192.168.1.100".to_string(),
+            "bob".to_string(),
+            "sip:bob@localhost".to_string(),
+            CallDirection::Internal,
+        )
+    }
+
+    #[tokio::test]
+    async fn test_transient_write_is_queued_and_flushed() {
+        let mut mock = MockCdrRepository::new();
+        let mut seq = mockall::Sequence::new();
+        mock.expect_create()
+            .times(1)
+            .in_sequence(&mut seq)
+            .returning(|_| Err("Failed to create CDR: pool timed out".to_string()));
+        mock.expect_create()
+            .times(1)
+            .in_sequence(&mut seq)
+            .returning(|_| Ok(()));
+        mock.expect_update().times(0);
+
+        let health = Arc::new(DbHealth::default());
+        let repo = ResilientCdrRepository::new(Arc::new(mock), health.clone());
+
+        let mut record = cdr("call-1");
+        repo.create(&record).await.unwrap();
+        assert!(health.is_degraded());
+        assert_eq!(repo.pending(), 1);
+
+        // Update while degraded collapses into the queued create
+        record.mark_ended(CallStatus::Completed, None, Some(200));
+        repo.update(&record).await.unwrap();
+        assert_eq!(repo.pending(), 1);
+
+        let queued = repo.get_by_call_id("call-1").await.unwrap().unwrap();
+        assert_eq!(queued.status, CallStatus::Completed);
+
+        health.record_success();
+        assert_eq!(repo.flush().await, 1);
+        assert_eq!(repo.pending(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_permanent_error_is_returned() {
+        let mut mock = MockCdrRepository::new();
+        mock.expect_create()
+            .returning(|_| Err("duplicate key value violates unique constraint".to_string()));
+
+        let health = Arc::new(DbHealth::default());
+        let repo = ResilientCdrRepository::new(Arc::new(mock), health.clone());
+
+        assert!(repo.create(&cdr("call-2")).await.is_err());
+        assert!(!health.is_degraded());
+        assert_eq!(repo.pending(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_queue_is_bounded() {
+        let health = Arc::new(DbHealth::default());
+        health.report_transient("connection refused");
+        let repo = ResilientCdrRepository::with_capacity(
+            Arc::new(MockCdrRepository::new()),
+            health,
+            2,
+        );
+
+        for i in 0..3 {
+            repo.create(&cdr(&format!("call-{}", i))).await.unwrap();
+        }
+
+        assert_eq!(repo.pending(), 2);
+        assert!(repo.queued_by(|c| c.call_id == "call-0").is_none());
+    }
+
+    #[tokio::test]
+    async fn test_journaled_writes_are_flushed_after_restart() {
+        let dir = std::env::temp_dir().join(format!("yakyak-cdr-restart-{}", Uuid::new_v4()));
+        let path = dir.join("cdr_journal.jsonl");
+
+        let health = Arc::new(DbHealth::default());
+        health.report_transient("connection refused");
+        let repo = ResilientCdrRepository::new(Arc::new(MockCdrRepository::new()), health)
+            .with_journal(CdrJournal::open(&path).unwrap());
+        repo.create(&cdr("call-1")).await.unwrap();
+        drop(repo);
+
+        let mut mock = MockCdrRepository::new();
+        mock.expect_create()
+            .withf(|cdr| cdr.call_id == "call-1")
+            .times(1)
+            .returning(|_| Ok(()));
+        let repo = ResilientCdrRepository::new(Arc::new(mock), Arc::new(DbHealth::default()))
+            .with_journal(CdrJournal::open(&path).unwrap());
+        assert_eq!(repo.pending(), 1);
+        assert_eq!(repo.flush().await, 1);
+        assert_eq!(CdrJournal::open(&path).unwrap().len(), 0);
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_list_is_served_from_cache_during_outage() {
+        let mut mock = MockCdrRepository::new();
+        let mut seq = mockall::Sequence::new();
+        mock.expect_list()
+            .times(1)
+            .in_sequence(&mut seq)
+            .returning(|_, _, _| Ok(vec![cdr("call-1")]));
+        mock.expect_list()
+            .times(2)
+            .in_sequence(&mut seq)
+            .returning(|_, _, _| Err("Failed to list CDRs: pool timed out".to_string()));
+        mock.expect_count()
+            .times(1)
+            .returning(|_| Err("Failed to count CDRs: pool timed out".to_string()));
+
+        let health = Arc::new(DbHealth::default());
+        let repo = ResilientCdrRepository::new(Arc::new(mock), health.clone());
+
+        assert_eq!(repo.list(CdrFilters::default(), 10, 0).await.unwrap().len(), 1);
+
+        let cached = repo.list(CdrFilters::default(), 10, 0).await.unwrap();
+        assert_eq!(cached[0].call_id, "call-1");
+        assert!(health.is_degraded());
+
+        // Queries never answered before still fail
+        assert!(repo.list(CdrFilters::default(), 10, 10).await.is_err());
+        assert!(repo.count(CdrFilters::default()).await.is_err());
+    }
+}
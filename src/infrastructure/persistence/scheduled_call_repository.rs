@@ -0,0 +1,235 @@
+//! PostgreSQL implementation of ScheduledCallRepository
+
+use crate::domain::campaign::{CampaignContent, RetryPolicy};
+use crate::domain::scheduled_call::{
+    Recurrence, ScheduledCall, ScheduledCallRepository, ScheduledCallStatus,
+};
+use async_trait::async_trait;
+use chrono::{DateTime, NaiveDateTime, Utc};
+use sqlx::{FromRow, PgPool};
+use tracing::{debug, error, warn};
+use uuid::Uuid;
+
+const COLUMNS: &str = "id, target, realm, local_time, time_zone, recurrence, content, \
+    confirm_digit, retry, notify_email, created_by, status, next_attempt_at, attempts, \
+    last_result, last_outcome, created_at, updated_at";
+
+#[derive(FromRow)]
+struct ScheduledCallRow {
+    id: Uuid,
+    target: String,
+    realm: Option<String>,
+    local_time: NaiveDateTime,
+    time_zone: String,
+    recurrence: Option<String>,
+    content: serde_json::Value,
+    confirm_digit: Option<String>,
+    retry: serde_json::Value,
+    notify_email: Option<String>,
+    created_by: String,
+    status: String,
+    next_attempt_at: DateTime<Utc>,
+    attempts: i32,
+    last_result: Option<String>,
+    last_outcome: Option<String>,
+    created_at: DateTime<Utc>,
+    updated_at: DateTime<Utc>,
+}
+
+impl TryFrom<ScheduledCallRow> for ScheduledCall {
+    type Error = String;
+
+    fn try_from(r: ScheduledCallRow) -> Result<Self, String> {
+        let content: CampaignContent = serde_json::from_value(r.content)
+            .map_err(|e| format!("Invalid content of scheduled call {}: {}", r.id, e))?;
+        let retry: RetryPolicy = serde_json::from_value(r.retry).unwrap_or_else(|e| {
+            warn!("Invalid retry policy of scheduled call {}: {}", r.id, e);
+            RetryPolicy::default()
+        });
+        let status = ScheduledCallStatus::parse(&r.status)
+            .ok_or_else(|| format!("Invalid status '{}' of scheduled call {}", r.status, r.id))?;
+        Ok(ScheduledCall {
+            id: r.id,
+            target: r.target,
+            realm: r.realm,
+            local_time: r.local_time,
+            time_zone: r.time_zone,
+            recurrence: r.recurrence.as_deref().and_then(Recurrence::parse),
+            content,
+            confirm_digit: r.confirm_digit.and_then(|d| d.chars().next()),
+            retry,
+            notify_email: r.notify_email,
+            created_by: r.created_by,
+            status,
+            next_attempt_at: r.next_attempt_at,
+            attempts: r.attempts.max(0) as u32,
+            last_result: r.last_result,
+            last_outcome: r.last_outcome.as_deref().and_then(ScheduledCallStatus::parse),
+            created_at: r.created_at,
+            updated_at: r.updated_at,
+        })
+    }
+}
+
+/// Rows that fail to convert are skipped, so one bad row does not stop
+/// every schedule
+fn convert(rows: Vec<ScheduledCallRow>) -> Vec<ScheduledCall> {
+    rows.into_iter()
+        .filter_map(|row| {
+            ScheduledCall::try_from(row)
+                .map_err(|e| error!("{}", e))
+                .ok()
+        })
+        .collect()
+}
+
+fn json(value: &impl serde::Serialize) -> serde_json::Value {
+    serde_json::to_value(value).unwrap_or(serde_json::Value::Null)
+}
+
+pub struct PgScheduledCallRepository {
+    pool: PgPool,
+}
+
+impl PgScheduledCallRepository {
+    pub fn new(pool: PgPool) -> Self {
+        Self { pool }
+    }
+}
+
+#[async_trait]
+impl ScheduledCallRepository for PgScheduledCallRepository {
+    async fn create(&self, call: &ScheduledCall) -> Result<(), String> {
+        debug!("Creating scheduled call {} to {}", call.id, call.target);
+
+        sqlx::query(&format!(
+            "INSERT INTO scheduled_calls ({}) \
+             VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12, $13, $14, $15, $16, $17, $18)",
+            COLUMNS
+        ))
+        .bind(call.id)
+        .bind(&call.target)
+        .bind(&call.realm)
+        .bind(call.local_time)
+        .bind(&call.time_zone)
+        .bind(call.recurrence.map(|r| r.as_str()))
+        .bind(json(&call.content))
+        .bind(call.confirm_digit.map(|d| d.to_string()))
+        .bind(json(&call.retry))
+        .bind(&call.notify_email)
+        .bind(&call.created_by)
+        .bind(call.status.as_str())
+        .bind(call.next_attempt_at)
+        .bind(call.attempts as i32)
+        .bind(&call.last_result)
+        .bind(call.last_outcome.map(|s| s.as_str()))
+        .bind(call.created_at)
+        .bind(call.updated_at)
+        .execute(&self.pool)
+        .await
+        .map_err(|e| {
+            error!("Failed to create scheduled call: {}", e);
+            format!("Database error: {}", e)
+        })?;
+
+        Ok(())
+    }
+
+    async fn get(&self, id: Uuid) -> Result<Option<ScheduledCall>, String> {
+        let row = sqlx::query_as::<_, ScheduledCallRow>(&format!(
+            "SELECT {} FROM scheduled_calls WHERE id = $1",
+            COLUMNS
+        ))
+        .bind(id)
+        .fetch_optional(&self.pool)
+        .await
+        .map_err(|e| {
+            error!("Failed to get scheduled call: {}", e);
+            format!("Database error: {}", e)
+        })?;
+
+        row.map(ScheduledCall::try_from).transpose()
+    }
+
+    async fn list(&self) -> Result<Vec<ScheduledCall>, String> {
+        let rows = sqlx::query_as::<_, ScheduledCallRow>(&format!(
+            "SELECT {} FROM scheduled_calls ORDER BY next_attempt_at",
+            COLUMNS
+        ))
+        .fetch_all(&self.pool)
+        .await
+        .map_err(|e| {
+            error!("Failed to list scheduled calls: {}", e);
+            format!("Database error: {}", e)
+        })?;
+
+        Ok(convert(rows))
+    }
+
+    async fn list_due(&self, now: DateTime<Utc>) -> Result<Vec<ScheduledCall>, String> {
+        let rows = sqlx::query_as::<_, ScheduledCallRow>(&format!(
+            "SELECT {} FROM scheduled_calls \
+             WHERE status = 'pending' AND next_attempt_at <= $1 \
+             ORDER BY next_attempt_at",
+            COLUMNS
+        ))
+        .bind(now)
+        .fetch_all(&self.pool)
+        .await
+        .map_err(|e| {
+            error!("Failed to list due scheduled calls: {}", e);
+            format!("Database error: {}", e)
+        })?;
+
+        Ok(convert(rows))
+    }
+
+    async fn update(&self, call: &ScheduledCall) -> Result<bool, String> {
+        let result = sqlx::query(
+            r#"
+            UPDATE scheduled_calls
+            SET target = $2, local_time = $3, time_zone = $4, recurrence = $5, content = $6,
+                confirm_digit = $7, retry = $8, notify_email = $9, status = $10,
+                next_attempt_at = $11, attempts = $12, last_result = $13, last_outcome = $14,
+                updated_at = $15
+            WHERE id = $1
+            "#,
+        )
+        .bind(call.id)
+        .bind(&call.target)
+        .bind(call.local_time)
+        .bind(&call.time_zone)
+        .bind(call.recurrence.map(|r| r.as_str()))
+        .bind(json(&call.content))
+        .bind(call.confirm_digit.map(|d| d.to_string()))
+        .bind(json(&call.retry))
+        .bind(&call.notify_email)
+        .bind(call.status.as_str())
+        .bind(call.next_attempt_at)
+        .bind(call.attempts as i32)
+        .bind(&call.last_result)
+        .bind(call.last_outcome.map(|s| s.as_str()))
+        .bind(call.updated_at)
+        .execute(&self.pool)
+        .await
+        .map_err(|e| {
+            error!("Failed to update scheduled call: {}", e);
+            format!("Database error: {}", e)
+        })?;
+
+        Ok(result.rows_affected() > 0)
+    }
+
+    async fn delete(&self, id: Uuid) -> Result<bool, String> {
+        let result = sqlx::query("DELETE FROM scheduled_calls WHERE id = $1")
+            .bind(id)
+            .execute(&self.pool)
+            .await
+            .map_err(|e| {
+                error!("Failed to delete scheduled call: {}", e);
+                format!("Database error: {}", e)
+            })?;
+
+        Ok(result.rows_affected() > 0)
+    }
+}
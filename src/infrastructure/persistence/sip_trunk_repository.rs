@@ -3,19 +3,90 @@ use crate::domain::sip_trunk::{
     CodecPreference, DtmfMode, SipTrunk, SipTrunkRepository, TrunkDirection, TrunkStatistics,
     TrunkType,
 };
+use crate::infrastructure::secrets::{open_secret, seal_secret, SecretKeyring};
 use async_trait::async_trait;
 use chrono::{DateTime, Utc};
 use sqlx::{PgPool, Row};
-use tracing::{debug, error};
+use std::sync::Arc;
+use tracing::{debug, error, info};
 use uuid::Uuid;
 
 pub struct PgSipTrunkRepository {
     pool: PgPool,
+    /// Seals stored passwords; they are kept in the clear without one
+    keyring: Option<Arc<SecretKeyring>>,
 }
 
 impl PgSipTrunkRepository {
     pub fn new(pool: PgPool) -> Self {
-        Self { pool }
+        Self { pool, keyring: None }
+    }
+
+    pub fn with_keyring(mut self, keyring: Arc<SecretKeyring>) -> Self {
+        self.keyring = Some(keyring);
+        self
+    }
+
+    /// Seal every stored password with the current key; returns how many
+    /// were rewritten
+    pub async fn reseal_passwords(&self) -> Result<usize, String> {
+        let keyring = self
+            .keyring
+            .as_deref()
+            .ok_or_else(|| "No secret key is configured".to_string())?;
+        let rows = sqlx::query("SELECT id, password FROM sip_trunks WHERE password IS NOT NULL")
+            .fetch_all(&self.pool)
+            .await
+            .map_err(|e| format!("Database error: {}", e))?;
+
+        let mut resealed = 0;
+        for row in rows {
+            let id: Uuid = row.get("id");
+            let stored: String = row.get("password");
+            let Some(sealed) = keyring
+                .reseal(&stored)
+                .map_err(|e| format!("Password of trunk {}: {}", id, e))?
+            else {
+                continue;
+            };
+            // Skipped when the password changed since it was read
+            let result = sqlx::query("UPDATE sip_trunks SET password = $2 WHERE id = $1 AND password = $3")
+                .bind(id)
+                .bind(&sealed)
+                .bind(&stored)
+                .execute(&self.pool)
+                .await
+                .map_err(|e| format!("Database error: {}", e))?;
+            resealed += result.rows_affected() as usize;
+        }
+        info!(
+            "Resealed {} trunk passwords with secret key {}",
+            resealed,
+            keyring.current_key_id()
+        );
+        Ok(resealed)
+    }
+
+    fn seal_password(&self, trunk: &SipTrunk) -> Option<String> {
+        trunk
+            .password
+            .as_ref()
+            .map(|password| seal_secret(self.keyring.as_deref(), password))
+    }
+
+    fn row_to_trunk(&self, row: sqlx::postgres::PgRow) -> Result<SipTrunk, String> {
+        let id: Uuid = row.get("id");
+        let password = row
+            .get::<Option<String>, _>("password")
+            .map(|stored| open_secret(self.keyring.as_deref(), &stored))
+            .transpose()
+            .map_err(|e| {
+                error!("Cannot read the password of SIP trunk {}: {}", id, e);
+                format!("Password of trunk {}: {}", id, e)
+            })?;
+        let mut trunk = row_to_trunk(row);
+        trunk.password = password;
+        Ok(trunk)
     }
 }
 
@@ -54,7 +125,7 @@ impl SipTrunkRepository for PgSipTrunkRepository {
         .bind(trunk.backup_server.as_ref())
         .bind(&direction_str)
         .bind(trunk.username.as_ref())
-        .bind(trunk.password.as_ref())
+        .bind(self.seal_password(&trunk))
         .bind(trunk.auth_username.as_ref())
         .bind(trunk.realm.as_ref())
         .bind(&allowed_ips_str)
@@ -107,7 +178,7 @@ impl SipTrunkRepository for PgSipTrunkRepository {
         .await;
 
         match result {
-            Ok(Some(row)) => Ok(Some(row_to_trunk(row))),
+            Ok(Some(row)) => self.row_to_trunk(row).map(Some),
             Ok(None) => Ok(None),
             Err(e) => {
                 error!("Failed to get SIP trunk: {}", e);
@@ -134,7 +205,7 @@ impl SipTrunkRepository for PgSipTrunkRepository {
         .await;
 
         match result {
-            Ok(Some(row)) => Ok(Some(row_to_trunk(row))),
+            Ok(Some(row)) => self.row_to_trunk(row).map(Some),
             Ok(None) => Ok(None),
             Err(e) => {
                 error!("Failed to get SIP trunk by name: {}", e);
@@ -177,7 +248,7 @@ impl SipTrunkRepository for PgSipTrunkRepository {
         .bind(trunk.backup_server.as_ref())
         .bind(&direction_str)
         .bind(trunk.username.as_ref())
-        .bind(trunk.password.as_ref())
+        .bind(self.seal_password(&trunk))
         .bind(trunk.auth_username.as_ref())
         .bind(trunk.realm.as_ref())
         .bind(&allowed_ips_str)
@@ -268,8 +339,7 @@ impl SipTrunkRepository for PgSipTrunkRepository {
 
         match result {
             Ok(rows) => {
-                let trunks: Vec<SipTrunk> = rows.iter().map(|row| row_to_trunk(row.clone())).collect();
-                Ok(trunks)
+                rows.into_iter().map(|row| self.row_to_trunk(row)).collect()
             }
             Err(e) => {
                 error!("Failed to list SIP trunks: {}", e);
@@ -355,6 +425,7 @@ impl SipTrunkRepository for PgSipTrunkRepository {
     }
 }
 
+/// Trunk of a row, without its password
 fn row_to_trunk(row: sqlx::postgres::PgRow) -> SipTrunk {
     let trunk_type_str: String = row.get("trunk_type");
     let trunk_type = match trunk_type_str.as_str() {
@@ -413,7 +484,7 @@ fn row_to_trunk(row: sqlx::postgres::PgRow) -> SipTrunk {
         backup_server: row.get("backup_server"),
         direction,
         username: row.get("username"),
-        password: row.get("password"),
+        password: None,
         auth_username: row.get("auth_username"),
         realm: row.get("realm"),
         allowed_ips,
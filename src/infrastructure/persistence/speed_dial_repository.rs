@@ -0,0 +1,170 @@
+//! PostgreSQL implementation of SpeedDialRepository
+
+use crate::domain::speed_dial::{SpeedDial, SpeedDialRepository};
+use async_trait::async_trait;
+use sqlx::{FromRow, PgPool};
+use tracing::{debug, error};
+use uuid::Uuid;
+
+#[derive(FromRow)]
+struct SpeedDialRow {
+    id: Uuid,
+    user_id: Option<i32>,
+    code: String,
+    target: String,
+    label: Option<String>,
+    created_at: chrono::DateTime<chrono::Utc>,
+    updated_at: chrono::DateTime<chrono::Utc>,
+}
+
+impl From<SpeedDialRow> for SpeedDial {
+    fn from(r: SpeedDialRow) -> Self {
+        SpeedDial {
+            id: r.id,
+            user_id: r.user_id,
+            code: r.code,
+            target: r.target,
+            label: r.label,
+            created_at: r.created_at,
+            updated_at: r.updated_at,
+        }
+    }
+}
+
+pub struct PgSpeedDialRepository {
+    pool: PgPool,
+}
+
+impl PgSpeedDialRepository {
+    pub fn new(pool: PgPool) -> Self {
+        Self { pool }
+    }
+}
+
+#[async_trait]
+impl SpeedDialRepository for PgSpeedDialRepository {
+    async fn create(&self, speed_dial: &SpeedDial) -> Result<(), String> {
+        debug!("Creating speed dial {} for user {:?}", speed_dial.code, speed_dial.user_id);
+
+        sqlx::query(
+            r#"
+            INSERT INTO speed_dials (id, user_id, code, target, label, created_at, updated_at)
+            VALUES ($1, $2, $3, $4, $5, $6, $7)
+            "#,
+        )
+        .bind(speed_dial.id)
+        .bind(speed_dial.user_id)
+        .bind(&speed_dial.code)
+        .bind(&speed_dial.target)
+        .bind(speed_dial.label.as_ref())
+        .bind(speed_dial.created_at)
+        .bind(speed_dial.updated_at)
+        .execute(&self.pool)
+        .await
+        .map_err(|e| {
+            error!("Failed to create speed dial: {}", e);
+            format!("Database error: {}", e)
+        })?;
+
+        Ok(())
+    }
+
+    async fn get_by_id(&self, id: Uuid) -> Result<Option<SpeedDial>, String> {
+        sqlx::query_as::<_, SpeedDialRow>(
+            r#"
+            SELECT id, user_id, code, target, label, created_at, updated_at
+            FROM speed_dials
+            WHERE id = $1
+            "#,
+        )
+        .bind(id)
+        .fetch_optional(&self.pool)
+        .await
+        .map(|row| row.map(Into::into))
+        .map_err(|e| {
+            error!("Failed to get speed dial: {}", e);
+            format!("Database error: {}", e)
+        })
+    }
+
+    async fn list_for_user(&self, user_id: i32) -> Result<Vec<SpeedDial>, String> {
+        let rows = sqlx::query_as::<_, SpeedDialRow>(
+            r#"
+            SELECT id, user_id, code, target, label, created_at, updated_at
+            FROM speed_dials
+            WHERE user_id = $1
+            ORDER BY code
+            "#,
+        )
+        .bind(user_id)
+        .fetch_all(&self.pool)
+        .await
+        .map_err(|e| {
+            error!("Failed to list speed dials for user {}: {}", user_id, e);
+            format!("Database error: {}", e)
+        })?;
+
+        Ok(rows.into_iter().map(Into::into).collect())
+    }
+
+    async fn list_company(&self) -> Result<Vec<SpeedDial>, String> {
+        let rows = sqlx::query_as::<_, SpeedDialRow>(
+            r#"
+            SELECT id, user_id, code, target, label, created_at, updated_at
+            FROM speed_dials
+            WHERE user_id IS NULL
+            ORDER BY code
+            "#,
+        )
+        .fetch_all(&self.pool)
+        .await
+        .map_err(|e| {
+            error!("Failed to list company speed dials: {}", e);
+            format!("Database error: {}", e)
+        })?;
+
+        Ok(rows.into_iter().map(Into::into).collect())
+    }
+
+    async fn update(&self, speed_dial: &SpeedDial) -> Result<(), String> {
+        let result = sqlx::query(
+            r#"
+            UPDATE speed_dials
+            SET code = $2, target = $3, label = $4, updated_at = $5
+            WHERE id = $1
+            "#,
+        )
+        .bind(speed_dial.id)
+        .bind(&speed_dial.code)
+        .bind(&speed_dial.target)
+        .bind(speed_dial.label.as_ref())
+        .bind(speed_dial.updated_at)
+        .execute(&self.pool)
+        .await
+        .map_err(|e| {
+            error!("Failed to update speed dial: {}", e);
+            format!("Database error: {}", e)
+        })?;
+
+        if result.rows_affected() == 0 {
+            return Err(format!("Speed dial not found: {}", speed_dial.id));
+        }
+        Ok(())
+    }
+
+    async fn delete(&self, id: Uuid) -> Result<(), String> {
+        let result = sqlx::query("DELETE FROM speed_dials WHERE id = $1")
+            .bind(id)
+            .execute(&self.pool)
+            .await
+            .map_err(|e| {
+                error!("Failed to delete speed dial: {}", e);
+                format!("Database error: {}", e)
+            })?;
+
+        if result.rows_affected() == 0 {
+            return Err(format!("Speed dial not found: {}", id));
+        }
+        Ok(())
+    }
+}
@@ -1,7 +1,10 @@
 //! PostgreSQL User Repository Implementation
 
 use crate::domain::shared::error::{DomainError, Result};
-use crate::domain::user::{ChangePassword, CreateUser, UpdateUser, User, UserRepository};
+use crate::domain::shared::SortOrder;
+use crate::domain::user::{
+    ChangePassword, CreateUser, DirectoryQuery, UpdateUser, User, UserRepository,
+};
 use async_trait::async_trait;
 use sqlx::PgPool;
 use tracing::{debug, info, warn};
@@ -29,6 +32,18 @@ impl PgUserRepository {
         format!("{:x}", digest)
     }
 
+    /// ORDER BY clause for a listing; ties are broken by ID so pages are stable
+    fn order_by(sort: &SortOrder) -> String {
+        let column = match sort.field.as_str() {
+            "id" => "id",
+            "username" => "username",
+            "display_name" => "display_name",
+            "realm" => "realm",
+            _ => "created_at",
+        };
+        format!("{} {}, id {}", column, sort.direction(), sort.direction())
+    }
+
     /// Verify a password against a hash
     fn verify_password(password: &str, hash: &str) -> Result<bool> {
         bcrypt::verify(password, hash)
@@ -50,9 +65,9 @@ impl UserRepository for PgUserRepository {
         // Insert into database
         let user = sqlx::query_as::<_, User>(
             r#"
-            INSERT INTO users (username, password_hash, sip_ha1, realm, display_name, email)
-            VALUES ($1, $2, $3, $4, $5, $6)
-            RETURNING id, username, password_hash, sip_ha1, realm, display_name, email, enabled, created_at, updated_at
+            INSERT INTO users (username, password_hash, sip_ha1, realm, display_name, email, department, locale, timezone)
+            VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9)
+            RETURNING id, username, password_hash, sip_ha1, realm, display_name, email, department, locale, timezone, enabled, created_at, updated_at
             "#,
         )
         .bind(&data.username)
@@ -61,6 +76,9 @@ impl UserRepository for PgUserRepository {
         .bind(&data.realm)
         .bind(&data.display_name)
         .bind(&data.email)
+        .bind(&data.department)
+        .bind(&data.locale)
+        .bind(&data.timezone)
         .fetch_one(&self.pool)
         .await
         .map_err(|e| {
@@ -84,7 +102,7 @@ impl UserRepository for PgUserRepository {
 
         let user = sqlx::query_as::<_, User>(
             r#"
-            SELECT id, username, password_hash, sip_ha1, realm, display_name, email, enabled, created_at, updated_at
+            SELECT id, username, password_hash, sip_ha1, realm, display_name, email, department, locale, timezone, enabled, created_at, updated_at
             FROM users
             WHERE id = $1
             "#,
@@ -102,7 +120,7 @@ impl UserRepository for PgUserRepository {
 
         let user = sqlx::query_as::<_, User>(
             r#"
-            SELECT id, username, password_hash, sip_ha1, realm, display_name, email, enabled, created_at, updated_at
+            SELECT id, username, password_hash, sip_ha1, realm, display_name, email, department, locale, timezone, enabled, created_at, updated_at
             FROM users
             WHERE username = $1
             "#,
@@ -124,7 +142,7 @@ impl UserRepository for PgUserRepository {
 
         let user = sqlx::query_as::<_, User>(
             r#"
-            SELECT id, username, password_hash, sip_ha1, realm, display_name, email, enabled, created_at, updated_at
+            SELECT id, username, password_hash, sip_ha1, realm, display_name, email, department, locale, timezone, enabled, created_at, updated_at
             FROM users
             WHERE username = $1 AND realm = $2
             "#,
@@ -138,17 +156,18 @@ impl UserRepository for PgUserRepository {
         Ok(user)
     }
 
-    async fn list(&self, limit: i64, offset: i64) -> Result<Vec<User>> {
+    async fn list(&self, sort: &SortOrder, limit: i64, offset: i64) -> Result<Vec<User>> {
         debug!("Listing users (limit: {}, offset: {})", limit, offset);
 
-        let users = sqlx::query_as::<_, User>(
+        let users = sqlx::query_as::<_, User>(&format!(
             r#"
-            SELECT id, username, password_hash, sip_ha1, realm, display_name, email, enabled, created_at, updated_at
+            SELECT id, username, password_hash, sip_ha1, realm, display_name, email, department, locale, timezone, enabled, created_at, updated_at
             FROM users
-            ORDER BY created_at DESC
+            ORDER BY {}
             LIMIT $1 OFFSET $2
             "#,
-        )
+            Self::order_by(sort)
+        ))
         .bind(limit)
         .bind(offset)
         .fetch_all(&self.pool)
@@ -158,21 +177,28 @@ impl UserRepository for PgUserRepository {
         Ok(users)
     }
 
-    async fn list_by_realm(&self, realm: &str, limit: i64, offset: i64) -> Result<Vec<User>> {
+    async fn list_by_realm(
+        &self,
+        realm: &str,
+        sort: &SortOrder,
+        limit: i64,
+        offset: i64,
+    ) -> Result<Vec<User>> {
         debug!(
             "Listing users by realm: {} (limit: {}, offset: {})",
             realm, limit, offset
         );
 
-        let users = sqlx::query_as::<_, User>(
+        let users = sqlx::query_as::<_, User>(&format!(
             r#"
-            SELECT id, username, password_hash, sip_ha1, realm, display_name, email, enabled, created_at, updated_at
+            SELECT id, username, password_hash, sip_ha1, realm, display_name, email, department, locale, timezone, enabled, created_at, updated_at
             FROM users
             WHERE realm = $1
-            ORDER BY created_at DESC
+            ORDER BY {}
             LIMIT $2 OFFSET $3
             "#,
-        )
+            Self::order_by(sort)
+        ))
         .bind(realm)
         .bind(limit)
         .bind(offset)
@@ -192,15 +218,21 @@ impl UserRepository for PgUserRepository {
             SET display_name = COALESCE($1, display_name),
                 email = COALESCE($2, email),
                 enabled = COALESCE($3, enabled),
+                department = COALESCE($5, department),
+                locale = COALESCE($6, locale),
+                timezone = COALESCE($7, timezone),
                 updated_at = CURRENT_TIMESTAMP
             WHERE id = $4
-            RETURNING id, username, password_hash, sip_ha1, realm, display_name, email, enabled, created_at, updated_at
+            RETURNING id, username, password_hash, sip_ha1, realm, display_name, email, department, locale, timezone, enabled, created_at, updated_at
             "#,
         )
         .bind(&data.display_name)
         .bind(&data.email)
         .bind(&data.enabled)
         .bind(id)
+        .bind(&data.department)
+        .bind(&data.locale)
+        .bind(&data.timezone)
         .fetch_one(&self.pool)
         .await
         .map_err(|e| match e {
@@ -323,6 +355,35 @@ impl UserRepository for PgUserRepository {
         Ok(count)
     }
 
+    async fn search(&self, query: &DirectoryQuery) -> Result<Vec<User>> {
+        debug!(
+            "Searching directory for '{}' (realm: {:?}, limit: {}, offset: {})",
+            query.text, query.realm, query.limit, query.offset
+        );
+
+        // Served by the trigram indexes on display_name, username and department
+        let users = sqlx::query_as::<_, User>(
+            r#"
+            SELECT id, username, password_hash, sip_ha1, realm, display_name, email, department, locale, timezone, enabled, created_at, updated_at
+            FROM users
+            WHERE enabled
+              AND ($1::VARCHAR IS NULL OR realm = $1)
+              AND (display_name ILIKE $2 OR username ILIKE $2 OR department ILIKE $2)
+            ORDER BY COALESCE(NULLIF(display_name, ''), username), id
+            LIMIT $3 OFFSET $4
+            "#,
+        )
+        .bind(&query.realm)
+        .bind(query.like_pattern())
+        .bind(query.limit)
+        .bind(query.offset)
+        .fetch_all(&self.pool)
+        .await
+        .map_err(|e| DomainError::Internal(format!("Failed to search users: {}", e)))?;
+
+        Ok(users)
+    }
+
     async fn verify_credentials(&self, username: &str, password: &str) -> Result<Option<User>> {
         debug!("Verifying credentials for user: {}", username);
 
@@ -0,0 +1,185 @@
+//! PostgreSQL implementation of VoicemailListRepository
+
+use crate::domain::voicemail_list::{
+    ListSendPermission, VoicemailDistributionList, VoicemailListRepository,
+};
+use async_trait::async_trait;
+use sqlx::types::Json;
+use sqlx::{FromRow, PgPool};
+use tracing::{debug, error};
+use uuid::Uuid;
+
+#[derive(FromRow)]
+struct VoicemailListRow {
+    id: Uuid,
+    name: String,
+    number: String,
+    owner_mailbox: String,
+    members: Vec<String>,
+    max_members: i32,
+    senders: Json<ListSendPermission>,
+    created_at: chrono::DateTime<chrono::Utc>,
+    updated_at: chrono::DateTime<chrono::Utc>,
+}
+
+impl From<VoicemailListRow> for VoicemailDistributionList {
+    fn from(r: VoicemailListRow) -> Self {
+        VoicemailDistributionList {
+            id: r.id,
+            name: r.name,
+            number: r.number,
+            owner: r.owner_mailbox,
+            members: r.members,
+            max_members: r.max_members.max(1) as u32,
+            senders: r.senders.0,
+            created_at: r.created_at,
+            updated_at: r.updated_at,
+        }
+    }
+}
+
+pub struct PgVoicemailListRepository {
+    pool: PgPool,
+}
+
+impl PgVoicemailListRepository {
+    pub fn new(pool: PgPool) -> Self {
+        Self { pool }
+    }
+}
+
+#[async_trait]
+impl VoicemailListRepository for PgVoicemailListRepository {
+    async fn create(&self, list: &VoicemailDistributionList) -> Result<(), String> {
+        debug!("Creating distribution list {} ({})", list.name, list.number);
+
+        sqlx::query(
+            r#"
+            INSERT INTO voicemail_distribution_lists
+                (id, name, number, owner_mailbox, members, max_members, senders,
+                 created_at, updated_at)
+            VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9)
+            "#,
+        )
+        .bind(list.id)
+        .bind(&list.name)
+        .bind(&list.number)
+        .bind(&list.owner)
+        .bind(&list.members)
+        .bind(list.max_members as i32)
+        .bind(Json(&list.senders))
+        .bind(list.created_at)
+        .bind(list.updated_at)
+        .execute(&self.pool)
+        .await
+        .map_err(|e| {
+            error!("Failed to create distribution list: {}", e);
+            format!("Database error: {}", e)
+        })?;
+
+        Ok(())
+    }
+
+    async fn get_by_id(&self, id: Uuid) -> Result<Option<VoicemailDistributionList>, String> {
+        sqlx::query_as::<_, VoicemailListRow>(
+            r#"
+            SELECT id, name, number, owner_mailbox, members, max_members, senders,
+                   created_at, updated_at
+            FROM voicemail_distribution_lists
+            WHERE id = $1
+            "#,
+        )
+        .bind(id)
+        .fetch_optional(&self.pool)
+        .await
+        .map(|row| row.map(Into::into))
+        .map_err(|e| {
+            error!("Failed to get distribution list: {}", e);
+            format!("Database error: {}", e)
+        })
+    }
+
+    async fn find_by_number(&self, number: &str) -> Result<Option<VoicemailDistributionList>, String> {
+        sqlx::query_as::<_, VoicemailListRow>(
+            r#"
+            SELECT id, name, number, owner_mailbox, members, max_members, senders,
+                   created_at, updated_at
+            FROM voicemail_distribution_lists
+            WHERE number = $1
+            "#,
+        )
+        .bind(number)
+        .fetch_optional(&self.pool)
+        .await
+        .map(|row| row.map(Into::into))
+        .map_err(|e| {
+            error!("Failed to find distribution list {}: {}", number, e);
+            format!("Database error: {}", e)
+        })
+    }
+
+    async fn list(&self) -> Result<Vec<VoicemailDistributionList>, String> {
+        let rows = sqlx::query_as::<_, VoicemailListRow>(
+            r#"
+            SELECT id, name, number, owner_mailbox, members, max_members, senders,
+                   created_at, updated_at
+            FROM voicemail_distribution_lists
+            ORDER BY number
+            "#,
+        )
+        .fetch_all(&self.pool)
+        .await
+        .map_err(|e| {
+            error!("Failed to list distribution lists: {}", e);
+            format!("Database error: {}", e)
+        })?;
+
+        Ok(rows.into_iter().map(Into::into).collect())
+    }
+
+    async fn update(&self, list: &VoicemailDistributionList) -> Result<(), String> {
+        let result = sqlx::query(
+            r#"
+            UPDATE voicemail_distribution_lists
+            SET name = $2, number = $3, owner_mailbox = $4, members = $5, max_members = $6,
+                senders = $7, updated_at = $8
+            WHERE id = $1
+            "#,
+        )
+        .bind(list.id)
+        .bind(&list.name)
+        .bind(&list.number)
+        .bind(&list.owner)
+        .bind(&list.members)
+        .bind(list.max_members as i32)
+        .bind(Json(&list.senders))
+        .bind(list.updated_at)
+        .execute(&self.pool)
+        .await
+        .map_err(|e| {
+            error!("Failed to update distribution list: {}", e);
+            format!("Database error: {}", e)
+        })?;
+
+        if result.rows_affected() == 0 {
+            return Err(format!("Distribution list not found: {}", list.id));
+        }
+        Ok(())
+    }
+
+    async fn delete(&self, id: Uuid) -> Result<(), String> {
+        let result = sqlx::query("DELETE FROM voicemail_distribution_lists WHERE id = $1")
+            .bind(id)
+            .execute(&self.pool)
+            .await
+            .map_err(|e| {
+                error!("Failed to delete distribution list: {}", e);
+                format!("Database error: {}", e)
+            })?;
+
+        if result.rows_affected() == 0 {
+            return Err(format!("Distribution list not found: {}", id));
+        }
+        Ok(())
+    }
+}
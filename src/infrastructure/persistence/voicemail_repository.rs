@@ -1,17 +1,34 @@
 /// PostgreSQL implementation of VoicemailRepository
+use crate::domain::privacy::{
+    ErasureSubject, Pseudonymizer, SubjectBearing, SubjectReference, SubjectStore,
+};
 use crate::domain::voicemail::{VoicemailMailbox, VoicemailMessage, VoicemailRepository, VoicemailStatus};
+use crate::domain::voicemail_service::VoicemailService;
 use async_trait::async_trait;
+use chrono::{DateTime, Utc};
 use sqlx::{PgPool, Row};
+use std::path::PathBuf;
 use tracing::{debug, error};
 use uuid::Uuid;
 
 pub struct PgVoicemailRepository {
     pool: PgPool,
+    /// Base directory of the audio files, for erasing them
+    audio_dir: Option<PathBuf>,
 }
 
 impl PgVoicemailRepository {
     pub fn new(pool: PgPool) -> Self {
-        Self { pool }
+        Self {
+            pool,
+            audio_dir: None,
+        }
+    }
+
+    /// Audio file paths are relative to `dir`
+    pub fn with_audio_dir(mut self, dir: PathBuf) -> Self {
+        self.audio_dir = Some(dir);
+        self
     }
 }
 
@@ -24,8 +41,8 @@ impl VoicemailRepository for PgVoicemailRepository {
             r#"
             INSERT INTO voicemail_messages
             (id, mailbox_id, caller, caller_name, duration_seconds, audio_file_path, audio_format,
-             status, created_at, read_at, saved_at)
-            VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11)
+             status, created_at, read_at, saved_at, transcript, transcript_confidence, via_list)
+            VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12, $13, $14)
             "#,
         )
         .bind(message.id)
@@ -39,6 +56,9 @@ impl VoicemailRepository for PgVoicemailRepository {
         .bind(message.created_at)
         .bind(message.read_at)
         .bind(message.saved_at)
+        .bind(message.transcript.as_ref())
+        .bind(message.transcript_confidence)
+        .bind(message.via_list.as_ref())
         .execute(&self.pool)
         .await;
 
@@ -58,7 +78,8 @@ impl VoicemailRepository for PgVoicemailRepository {
         let result = sqlx::query(
             r#"
             SELECT id, mailbox_id, caller, caller_name, duration_seconds, audio_file_path,
-                   audio_format, status, created_at, read_at, saved_at
+                   audio_format, status, created_at, read_at, saved_at, transcript,
+                   transcript_confidence, via_list
             FROM voicemail_messages
             WHERE id = $1
             "#,
@@ -90,6 +111,9 @@ impl VoicemailRepository for PgVoicemailRepository {
                     created_at: row.get("created_at"),
                     read_at: row.get("read_at"),
                     saved_at: row.get("saved_at"),
+                    transcript: row.get("transcript"),
+                    transcript_confidence: row.get("transcript_confidence"),
+                    via_list: row.get("via_list"),
                 };
 
                 Ok(Some(message))
@@ -112,7 +136,8 @@ impl VoicemailRepository for PgVoicemailRepository {
             sqlx::query(
                 r#"
                 SELECT id, mailbox_id, caller, caller_name, duration_seconds, audio_file_path,
-                       audio_format, status, created_at, read_at, saved_at
+                       audio_format, status, created_at, read_at, saved_at, transcript,
+                       transcript_confidence, via_list
                 FROM voicemail_messages
                 WHERE mailbox_id = $1 AND status = $2
                 ORDER BY created_at DESC
@@ -124,7 +149,8 @@ impl VoicemailRepository for PgVoicemailRepository {
             sqlx::query(
                 r#"
                 SELECT id, mailbox_id, caller, caller_name, duration_seconds, audio_file_path,
-                       audio_format, status, created_at, read_at, saved_at
+                       audio_format, status, created_at, read_at, saved_at, transcript,
+                       transcript_confidence, via_list
                 FROM voicemail_messages
                 WHERE mailbox_id = $1
                 ORDER BY created_at DESC
@@ -161,6 +187,9 @@ impl VoicemailRepository for PgVoicemailRepository {
                             created_at: row.get("created_at"),
                             read_at: row.get("read_at"),
                             saved_at: row.get("saved_at"),
+                            transcript: row.get("transcript"),
+                            transcript_confidence: row.get("transcript_confidence"),
+                            via_list: row.get("via_list"),
                         }
                     })
                     .collect();
@@ -235,6 +264,32 @@ impl VoicemailRepository for PgVoicemailRepository {
         }
     }
 
+    async fn set_transcript(&self, id: Uuid, transcript: &str, confidence: Option<f32>) -> Result<(), String> {
+        let result = sqlx::query(
+            r#"
+            UPDATE voicemail_messages
+            SET transcript = $2, transcript_confidence = $3
+            WHERE id = $1
+            "#,
+        )
+        .bind(id)
+        .bind(transcript)
+        .bind(confidence)
+        .execute(&self.pool)
+        .await;
+
+        match result {
+            Ok(_) => {
+                debug!("Stored transcript of voicemail message: {}", id);
+                Ok(())
+            }
+            Err(e) => {
+                error!("Failed to store voicemail transcript: {}", e);
+                Err(format!("Database error: {}", e))
+            }
+        }
+    }
+
     async fn delete_message(&self, id: Uuid) -> Result<(), String> {
         let result = sqlx::query("DELETE FROM voicemail_messages WHERE id = $1")
             .bind(id)
@@ -283,11 +338,29 @@ impl VoicemailRepository for PgVoicemailRepository {
         }
     }
 
+    async fn count_audio_references(&self, audio_file_path: &str) -> Result<u32, String> {
+        let result = sqlx::query_scalar::<_, i64>(
+            "SELECT COUNT(*) FROM voicemail_messages WHERE audio_file_path = $1",
+        )
+        .bind(audio_file_path)
+        .fetch_one(&self.pool)
+        .await;
+
+        match result {
+            Ok(count) => Ok(count as u32),
+            Err(e) => {
+                error!("Failed to count voicemail audio references: {}", e);
+                Err(format!("Database error: {}", e))
+            }
+        }
+    }
+
     async fn get_mailbox(&self, mailbox_id: &str) -> Result<Option<VoicemailMailbox>, String> {
         let result = sqlx::query(
             r#"
-            SELECT mailbox_id, user_id, pin, greeting_file, max_message_duration,
-                   max_messages, email_notification, email_address, created_at, updated_at
+            SELECT mailbox_id, user_id, pin, greeting_file, name_greeting_file,
+                   max_message_duration, max_messages, email_notification, email_address,
+                   created_at, updated_at
             FROM voicemail_mailboxes
             WHERE mailbox_id = $1
             "#,
@@ -303,6 +376,7 @@ impl VoicemailRepository for PgVoicemailRepository {
                     user_id: row.get("user_id"),
                     pin: row.get("pin"),
                     greeting_file: row.get("greeting_file"),
+                    name_greeting_file: row.get("name_greeting_file"),
                     max_message_duration: row.get::<i32, _>("max_message_duration") as u32,
                     max_messages: row.get::<i32, _>("max_messages") as u32,
                     email_notification: row.get("email_notification"),
@@ -327,7 +401,8 @@ impl VoicemailRepository for PgVoicemailRepository {
             r#"
             UPDATE voicemail_mailboxes
             SET pin = $2, greeting_file = $3, max_message_duration = $4,
-                max_messages = $5, email_notification = $6, email_address = $7, updated_at = $8
+                max_messages = $5, email_notification = $6, email_address = $7, updated_at = $8,
+                name_greeting_file = $9
             WHERE mailbox_id = $1
             "#,
         )
@@ -339,6 +414,7 @@ impl VoicemailRepository for PgVoicemailRepository {
         .bind(mailbox.email_notification)
         .bind(mailbox.email_address.as_ref())
         .bind(mailbox.updated_at)
+        .bind(mailbox.name_greeting_file.as_ref())
         .execute(&self.pool)
         .await;
 
@@ -353,8 +429,9 @@ impl VoicemailRepository for PgVoicemailRepository {
                     r#"
                     INSERT INTO voicemail_mailboxes
                     (mailbox_id, user_id, pin, greeting_file, max_message_duration,
-                     max_messages, email_notification, email_address, created_at, updated_at)
-                    VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10)
+                     max_messages, email_notification, email_address, created_at, updated_at,
+                     name_greeting_file)
+                    VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11)
                     "#,
                 )
                 .bind(&mailbox.mailbox_id)
@@ -367,6 +444,7 @@ impl VoicemailRepository for PgVoicemailRepository {
                 .bind(mailbox.email_address.as_ref())
                 .bind(mailbox.created_at)
                 .bind(mailbox.updated_at)
+                .bind(mailbox.name_greeting_file.as_ref())
                 .execute(&self.pool)
                 .await;
 
@@ -385,6 +463,108 @@ impl VoicemailRepository for PgVoicemailRepository {
     }
 }
 
+#[async_trait]
+impl SubjectStore for PgVoicemailRepository {
+    fn name(&self) -> &'static str {
+        "voicemail"
+    }
+
+    fn subject_fields(&self) -> &'static [&'static str] {
+        VoicemailMessage::SUBJECT_FIELDS
+    }
+
+    async fn find_subject(&self, subject: &ErasureSubject) -> Result<Vec<SubjectReference>, String> {
+        let rows = sqlx::query(
+            r#"
+            SELECT id, caller, caller_name, audio_file_path, created_at
+            FROM voicemail_messages
+            WHERE caller ILIKE ANY($1) OR caller_name ILIKE ANY($1)
+            "#,
+        )
+        .bind(subject.search_patterns())
+        .fetch_all(&self.pool)
+        .await
+        .map_err(|e| {
+            error!("Failed to search voicemail for an erasure subject: {}", e);
+            format!("Database error: {}", e)
+        })?;
+
+        Ok(rows
+            .iter()
+            .filter_map(|row| {
+                let fields: Vec<String> = [
+                    ("caller", Some(row.get::<String, _>("caller"))),
+                    ("caller_name", row.get::<Option<String>, _>("caller_name")),
+                ]
+                .into_iter()
+                .filter(|(_, value)| value.as_deref().is_some_and(|v| subject.matches(v)))
+                .map(|(field, _)| field.to_string())
+                .collect();
+                (!fields.is_empty()).then(|| SubjectReference {
+                    record_id: row.get::<Uuid, _>("id").to_string(),
+                    at: row.get::<DateTime<Utc>, _>("created_at"),
+                    fields,
+                    media: Some(row.get("audio_file_path")),
+                })
+            })
+            .collect())
+    }
+
+    async fn pseudonymize(
+        &self,
+        subject: &ErasureSubject,
+        pseudonyms: &Pseudonymizer,
+        records: &[SubjectReference],
+    ) -> Result<u64, String> {
+        let mut count = 0;
+        for record in records {
+            let Ok(id) = record.record_id.parse::<Uuid>() else {
+                continue;
+            };
+            // Erased with its media already
+            let Some(mut message) = self.get_message(id).await? else {
+                continue;
+            };
+            if !message.pseudonymize(subject, pseudonyms) {
+                continue;
+            }
+            sqlx::query("UPDATE voicemail_messages SET caller = $2, caller_name = $3 WHERE id = $1")
+                .bind(id)
+                .bind(&message.caller)
+                .bind(message.caller_name.as_ref())
+                .execute(&self.pool)
+                .await
+                .map_err(|e| {
+                    error!("Failed to pseudonymize voicemail message {}: {}", id, e);
+                    format!("Database error: {}", e)
+                })?;
+            count += 1;
+        }
+        debug!("Pseudonymized {} voicemail messages", count);
+        Ok(count)
+    }
+
+    /// Messages go with their recordings, which are kept while other copies
+    /// of a list message still use them
+    async fn erase_media(&self, records: &[SubjectReference]) -> Result<u64, String> {
+        let Some(dir) = &self.audio_dir else {
+            return Err("No voicemail directory to delete recordings from".to_string());
+        };
+        let service = VoicemailService::new(dir);
+        let mut count = 0;
+        for record in records {
+            let Ok(id) = record.record_id.parse::<Uuid>() else {
+                continue;
+            };
+            if let Some(message) = self.get_message(id).await? {
+                service.delete_message(self, &message).await?;
+                count += 1;
+            }
+        }
+        Ok(count)
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -0,0 +1,152 @@
+//! Host and address handling for SIP headers
+//!
+//! IPv6 literals must be bracketed in Via, Contact, Route and Request-URIs
+//! (`[2001:db8::1]:5060`, RFC 3261 25.1). The rsip parser does not handle
+//! bracketed hosts, so header values carrying addresses are parsed here.
+
+use std::net::{IpAddr, SocketAddr};
+
+/// Default SIP port
+pub const DEFAULT_SIP_PORT: u16 = 5060;
+
+/// Host part for a SIP header: IPv6 literals are bracketed
+pub fn format_host(ip: IpAddr) -> String {
+    match ip {
+        IpAddr::V4(v4) => v4.to_string(),
+        IpAddr::V6(v6) => format!("[{}]", v6),
+    }
+}
+
+/// `host:port` for a SIP header (`[2001:db8::1]:5060` for IPv6)
+pub fn format_host_port(addr: SocketAddr) -> String {
+    format!("{}:{}", format_host(addr.ip()), addr.port())
+}
+
+/// Address family keyword for SDP (`IP4` or `IP6`)
+pub fn sdp_address_type(ip: IpAddr) -> &'static str {
+    if ip.is_ipv4() {
+        "IP4"
+    } else {
+        "IP6"
+    }
+}
+
+/// Parse `host[:port]` where host is an IP literal
+///
+/// Accepts `192.0.2.1`, `192.0.2.1:5062`, `[2001:db8::1]`,
+/// `[2001:db8::1]:5062` and an unbracketed IPv6 address (taken as having
+/// no port).
+pub fn parse_host_port(host_port: &str, default_port: u16) -> Option<SocketAddr> {
+    let host_port = host_port.trim();
+
+    if let Some(rest) = host_port.strip_prefix('[') {
+        let (host, after) = rest.split_once(']')?;
+        let ip: IpAddr = host.parse().ok()?;
+        let port = match after.strip_prefix(':') {
+            Some(port) => port.parse().ok()?,
+            None if after.is_empty() => default_port,
+            None => return None,
+        };
+        return Some(SocketAddr::new(ip, port));
+    }
+
+    if let Ok(ip) = host_port.parse::<IpAddr>() {
+        return Some(SocketAddr::new(ip, default_port));
+    }
+
+    let (host, port) = host_port.rsplit_once(':')?;
+    Some(SocketAddr::new(host.parse().ok()?, port.parse().ok()?))
+}
+
+/// URI inside a name-addr (`"Bob" <sip:bob@host>;expires=60`) or addr-spec
+pub fn uri_from_header(value: &str) -> &str {
+    let value = value.trim();
+    match (value.find('<'), value.find('>')) {
+        (Some(start), Some(end)) if start < end => &value[start + 1..end],
+        _ => value.split(';').next().unwrap_or(value).trim(),
+    }
+}
+
+/// `host[:port]` part of a SIP URI, without user, parameters or headers
+pub fn uri_host_port(uri: &str) -> &str {
+    let uri = uri_from_header(uri);
+    let rest = uri
+        .strip_prefix("sips:")
+        .or_else(|| uri.strip_prefix("sip:"))
+        .unwrap_or(uri);
+    let host_port = rest.rsplit_once('@').map(|(_, h)| h).unwrap_or(rest);
+    host_port
+        .split([';', '?'])
+        .next()
+        .unwrap_or(host_port)
+}
+
+/// Socket address of a SIP URI or Contact value with an IP literal host
+pub fn uri_socket_addr(uri: &str) -> Option<SocketAddr> {
+    parse_host_port(uri_host_port(uri), DEFAULT_SIP_PORT)
+}
+
+/// Sent-by address of a Via header value (`SIP/2.0/UDP [::1]:5060;branch=...`)
+pub fn via_sent_by(via: &str) -> Option<SocketAddr> {
+    let value = via.strip_prefix("Via:").unwrap_or(via).trim();
+    let sent_by = value.split_whitespace().nth(1)?.split(';').next()?;
+    parse_host_port(sent_by, DEFAULT_SIP_PORT)
+}
+
+/// The unspecified address of the same family as `ip` (bind "any")
+pub fn unspecified_like(ip: IpAddr) -> IpAddr {
+    match ip {
+        IpAddr::V4(_) => IpAddr::V4(std::net::Ipv4Addr::UNSPECIFIED),
+        IpAddr::V6(_) => IpAddr::V6(std::net::Ipv6Addr::UNSPECIFIED),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_format_bracketed_ipv6() {
+        let v6: SocketAddr = "[2001:db8::1]:5060".parse().unwrap();
+        assert_eq!(format_host_port(v6), "[2001:db8::1]:5060");
+        assert_eq!(format_host("192.0.2.1".parse().unwrap()), "192.0.2.1");
+        assert_eq!(sdp_address_type(v6.ip()), "IP6");
+    }
+
+    #[test]
+    fn test_parse_host_port() {
+        assert_eq!(
+            parse_host_port("[2001:db8::1]:5062", 5060),
+            Some("[2001:db8::1]:5062".parse().unwrap())
+        );
+        assert_eq!(
+            parse_host_port("[2001:db8::1]", 5060),
+            Some("[2001:db8::1]:5060".parse().unwrap())
+        );
+        assert_eq!(
+            parse_host_port("2001:db8::1", 5060),
+            Some("[2001:db8::1]:5060".parse().unwrap())
+        );
+        assert_eq!(
+            parse_host_port("192.0.2.1:5070", 5060),
+            Some("192.0.2.1:5070".parse().unwrap())
+        );
+        assert_eq!(parse_host_port("pbx.example.com:5060", 5060), None);
+    }
+
+    #[test]
+    fn test_contact_and_via_addresses() {
+        assert_eq!(
+            uri_socket_addr("\"Bob\" <sip:bob@[2001:db8::7]:5062;transport=udp>;expires=60"),
+            Some("[2001:db8::7]:5062".parse().unwrap())
+        );
+        assert_eq!(
+            uri_socket_addr("sip:alice@192.0.2.4"),
+            Some("192.0.2.4:5060".parse().unwrap())
+        );
+        assert_eq!(
+            via_sent_by("SIP/2.0/UDP [::1]:5066;branch=z9hG4bK1;rport"),
+            Some("[::1]:5066".parse().unwrap())
+        );
+    }
+}
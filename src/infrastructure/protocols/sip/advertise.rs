@@ -0,0 +1,293 @@
+//! Address advertisement behind NAT
+//!
+//! When yakyak runs behind NAT (e.g. a cloud VM with a floating IP) the
+//! address it binds to is not reachable from outside. The external address,
+//! configured statically or learned through STUN, is advertised instead in
+//! SDP connection lines, in the Contact of requests we originate and in
+//! their Via sent-by, while sockets stay bound to the local address.
+//!
+//! Peers inside the configured local subnets keep getting the local address
+//! so LAN phones do not hairpin through the NAT. The peer is identified per
+//! request by the top Via (its `received` parameter when present).
+
+use super::address::{format_host_port, via_sent_by, DEFAULT_SIP_PORT};
+use super::message::SipRequest;
+use super::rport::extract_received_from_via;
+use crate::infrastructure::protocols::stun::StunClient;
+use rsip::headers::UntypedHeader;
+use rsip::Header;
+use serde::{Deserialize, Serialize};
+use std::net::{IpAddr, SocketAddr};
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+use tokio::task::JoinHandle;
+use tracing::{debug, info, warn};
+
+/// External address settings
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ExternalAddressConfig {
+    /// Static external address; takes precedence over STUN
+    pub advertised_address: Option<IpAddr>,
+    /// STUN server (`host:port`) used to discover the external address
+    pub stun_server: Option<String>,
+    /// Seconds between STUN re-checks
+    pub stun_recheck_secs: u64,
+    /// Peers in these subnets (`10.0.0.0/8`, `fd00::/8`) get the local address
+    pub local_subnets: Vec<String>,
+}
+
+impl Default for ExternalAddressConfig {
+    fn default() -> Self {
+        Self {
+            advertised_address: None,
+            stun_server: None,
+            stun_recheck_secs: 300,
+            local_subnets: Vec::new(),
+        }
+    }
+}
+
+/// IP network in CIDR notation
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Subnet {
+    network: IpAddr,
+    prefix: u8,
+}
+
+impl Subnet {
+    /// Parse `addr/prefix`; a bare address is a single host
+    pub fn parse(cidr: &str) -> Result<Self, String> {
+        let (addr, prefix) = match cidr.trim().split_once('/') {
+            Some((addr, prefix)) => (addr, Some(prefix)),
+            None => (cidr.trim(), None),
+        };
+        let network: IpAddr = addr
+            .parse()
+            .map_err(|_| format!("Invalid subnet address: {}", cidr))?;
+        let max = if network.is_ipv4() { 32 } else { 128 };
+        let prefix = match prefix {
+            Some(prefix) => prefix
+                .parse::<u8>()
+                .ok()
+                .filter(|p| *p <= max)
+                .ok_or_else(|| format!("Invalid subnet prefix: {}", cidr))?,
+            None => max,
+        };
+        Ok(Self { network, prefix })
+    }
+
+    pub fn contains(&self, ip: IpAddr) -> bool {
+        match (self.network, ip) {
+            (IpAddr::V4(network), IpAddr::V4(ip)) => {
+                let mask = u32::MAX.checked_shl(32 - self.prefix as u32).unwrap_or(0);
+                u32::from(network) & mask == u32::from(ip) & mask
+            }
+            (IpAddr::V6(network), IpAddr::V6(ip)) => {
+                let mask = u128::MAX.checked_shl(128 - self.prefix as u32).unwrap_or(0);
+                u128::from(network) & mask == u128::from(ip) & mask
+            }
+            _ => false,
+        }
+    }
+}
+
+/// Picks the address advertised to each peer
+pub struct AddressAdvertiser {
+    external: RwLock<Option<IpAddr>>,
+    local_subnets: Vec<Subnet>,
+}
+
+impl AddressAdvertiser {
+    pub fn new(external: Option<IpAddr>, local_subnets: Vec<Subnet>) -> Self {
+        Self {
+            external: RwLock::new(external),
+            local_subnets,
+        }
+    }
+
+    pub fn from_config(config: &ExternalAddressConfig) -> Result<Self, String> {
+        let local_subnets = config
+            .local_subnets
+            .iter()
+            .map(|cidr| Subnet::parse(cidr))
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(Self::new(config.advertised_address, local_subnets))
+    }
+
+    /// Current external address, if known
+    pub fn external(&self) -> Option<IpAddr> {
+        *self.external.read().unwrap()
+    }
+
+    pub fn set_external(&self, external: IpAddr) {
+        let previous = self.external.write().unwrap().replace(external);
+        if previous != Some(external) {
+            info!("External address is now {}", external);
+        }
+    }
+
+    /// Whether `peer` is in one of the local subnets
+    pub fn is_local_peer(&self, peer: IpAddr) -> bool {
+        self.local_subnets
+            .iter()
+            .any(|subnet| subnet.contains(peer))
+    }
+
+    /// External address to show `peer`; `None` when the local address is
+    /// right (no external address, or a peer on a local subnet)
+    pub fn external_for(&self, peer: Option<IpAddr>) -> Option<IpAddr> {
+        match peer {
+            Some(peer) if self.is_local_peer(peer) => None,
+            _ => self.external(),
+        }
+    }
+
+    /// Address to advertise to `peer` for the local address `local`
+    ///
+    /// The external address only stands in for a local address of the
+    /// same family.
+    pub fn advertise(&self, local: IpAddr, peer: Option<IpAddr>) -> IpAddr {
+        self.external_for(peer)
+            .filter(|external| external.is_ipv4() == local.is_ipv4())
+            .unwrap_or(local)
+    }
+
+    /// `host:port` to advertise to `peer` for the local `host:port`
+    pub fn advertise_host_port(&self, local: &str, peer: Option<IpAddr>) -> String {
+        match self.external_for(peer) {
+            Some(external) => {
+                let port = local
+                    .rsplit_once(':')
+                    .and_then(|(_, port)| port.parse().ok())
+                    .unwrap_or(DEFAULT_SIP_PORT);
+                format_host_port(SocketAddr::new(external, port))
+            }
+            None => local.to_string(),
+        }
+    }
+
+    /// Re-learn the external address from `server` every `interval`
+    ///
+    /// Does nothing useful when a static address is configured; callers
+    /// only start it without one.
+    pub fn spawn_stun_discovery(
+        self: Arc<Self>,
+        server: SocketAddr,
+        interval: Duration,
+    ) -> JoinHandle<()> {
+        tokio::spawn(async move {
+            let mut ticker = tokio::time::interval(interval.max(Duration::from_secs(1)));
+            loop {
+                ticker.tick().await;
+                let client = StunClient::new(server);
+                let result = tokio::task::spawn_blocking(move || {
+                    client.binding_request(SocketAddr::from(([0, 0, 0, 0], 0)))
+                })
+                .await;
+                match result {
+                    Ok(Ok(result)) => {
+                        debug!("STUN mapped address: {}", result.public_addr);
+                        self.set_external(result.public_addr.ip());
+                    }
+                    Ok(Err(e)) => warn!("STUN discovery via {} failed: {}", server, e),
+                    Err(e) => warn!("STUN discovery task failed: {}", e),
+                }
+            }
+        })
+    }
+}
+
+/// Source address of the peer that sent `request` (top Via `received`,
+/// else its sent-by)
+pub fn peer_ip(request: &SipRequest) -> Option<IpAddr> {
+    let via = request.headers().iter().find_map(|h| match h {
+        Header::Via(via) => Some(via.value().to_string()),
+        _ => None,
+    })?;
+    extract_received_from_via(&via)
+        .and_then(|received| received.parse().ok())
+        .or_else(|| via_sent_by(&via).map(|addr| addr.ip()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ip(s: &str) -> IpAddr {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn test_static_external_address() {
+        let advertiser = AddressAdvertiser::from_config(&ExternalAddressConfig {
+            advertised_address: Some(ip("203.0.113.10")),
+            ..Default::default()
+        })
+        .unwrap();
+        let local = ip("10.0.0.5");
+
+        assert_eq!(
+            advertiser.advertise(local, Some(ip("198.51.100.7"))),
+            ip("203.0.113.10")
+        );
+        assert_eq!(advertiser.advertise(local, None), ip("203.0.113.10"));
+        // IPv6 media keeps its own address
+        assert_eq!(
+            advertiser.advertise(ip("2001:db8::5"), None),
+            ip("2001:db8::5")
+        );
+        assert_eq!(
+            advertiser.advertise_host_port("10.0.0.5:5060", None),
+            "203.0.113.10:5060"
+        );
+
+        // Nothing to advertise until an address is known
+        let unknown = AddressAdvertiser::new(None, Vec::new());
+        assert_eq!(unknown.advertise(local, Some(ip("198.51.100.7"))), local);
+        unknown.set_external(ip("203.0.113.20"));
+        assert_eq!(
+            unknown.advertise(local, Some(ip("198.51.100.7"))),
+            ip("203.0.113.20")
+        );
+    }
+
+    #[test]
+    fn test_local_subnets_get_local_address() {
+        let advertiser = AddressAdvertiser::from_config(&ExternalAddressConfig {
+            advertised_address: Some(ip("203.0.113.10")),
+            local_subnets: vec!["192.168.0.0/16".to_string(), "fd00::/8".to_string()],
+            ..Default::default()
+        })
+        .unwrap();
+        let local = ip("192.168.1.2");
+
+        assert_eq!(advertiser.advertise(local, Some(ip("192.168.44.9"))), local);
+        assert_eq!(
+            advertiser.advertise(local, Some(ip("192.169.0.1"))),
+            ip("203.0.113.10")
+        );
+        assert!(advertiser.is_local_peer(ip("fd12::1")));
+        assert_eq!(
+            advertiser.advertise_host_port("192.168.1.2:5060", Some(ip("192.168.44.9"))),
+            "192.168.1.2:5060"
+        );
+
+        // The peer is taken from the request's top Via
+        let request = SipRequest::parse(
+            b"INVITE sip:bob@example.com SIP/2.0\r\n\
+              Via: SIP/2.0/UDP 192.168.44.9:5060;branch=z9hG4bK1;received=198.51.100.7\r\n\
+              Call-ID: nat\r\nCSeq: 1 INVITE\r\n\r\n",
+        )
+        .unwrap();
+        assert_eq!(peer_ip(&request), Some(ip("198.51.100.7")));
+        assert_eq!(
+            advertiser.advertise(local, peer_ip(&request)),
+            ip("203.0.113.10")
+        );
+
+        assert!(Subnet::parse("10.0.0.0/33").is_err());
+        assert!(Subnet::parse("example.com/8").is_err());
+        assert!(Subnet::parse("10.1.2.3").unwrap().contains(ip("10.1.2.3")));
+    }
+}
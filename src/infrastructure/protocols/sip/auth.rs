@@ -1,8 +1,10 @@
 //! SIP Digest Authentication (RFC 2617, RFC 3261)
 
+use super::auth_enhanced::{DigestAlgorithm, EnhancedDigestAuth};
 use super::message::{SipError, SipRequest};
 use async_trait::async_trait;
 use rand::Rng;
+use rsip::headers::UntypedHeader;
 use rsip::Header;
 use std::collections::HashMap;
 use std::sync::Arc;
@@ -34,6 +36,9 @@ pub struct AuthChallenge {
     pub nonce: String,
     pub algorithm: String,
     pub qop: Option<String>,
+    pub opaque: Option<String>,
+    /// The nonce of the answered request was stale, not its credentials
+    pub stale: bool,
 }
 
 impl AuthChallenge {
@@ -44,9 +49,75 @@ impl AuthChallenge {
             nonce: Self::generate_nonce(),
             algorithm: "MD5".to_string(),
             qop: Some("auth".to_string()),
+            opaque: None,
+            stale: false,
         }
     }
 
+    /// Parse a WWW-Authenticate or Proxy-Authenticate header value
+    /// received from an upstream server
+    pub fn parse(value: &str) -> Option<Self> {
+        let params = AuthorizationHeader::parse_digest_params(value).ok()?;
+        Some(Self {
+            realm: params.get("realm")?.to_string(),
+            nonce: params.get("nonce")?.to_string(),
+            algorithm: params
+                .get("algorithm")
+                .cloned()
+                .unwrap_or_else(|| "MD5".to_string()),
+            // Only qop=auth is supported; "auth,auth-int" is split by the parser
+            qop: params.get("qop").map(|_| "auth".to_string()),
+            opaque: params.get("opaque").cloned(),
+            stale: params
+                .get("stale")
+                .is_some_and(|stale| stale.eq_ignore_ascii_case("true")),
+        })
+    }
+
+    /// Authorization header value answering this challenge (client side)
+    ///
+    /// `nc` counts the requests sent with this nonce, starting at 1.
+    pub fn authorize(
+        &self,
+        username: &str,
+        password: &str,
+        method: &str,
+        uri: &str,
+        nc: u32,
+    ) -> String {
+        let algorithm = DigestAlgorithm::from_str(&self.algorithm).unwrap_or(DigestAlgorithm::MD5);
+        let ha1 = EnhancedDigestAuth::calculate_ha1(username, &self.realm, password, algorithm);
+        let ha2 = EnhancedDigestAuth::calculate_ha2(method, uri, algorithm);
+
+        let mut value = format!(
+            r#"Digest username="{}", realm="{}", nonce="{}", uri="{}", algorithm={}"#,
+            username, self.realm, self.nonce, uri, self.algorithm
+        );
+        let response = match &self.qop {
+            Some(qop) => {
+                let nc = format!("{:08x}", nc);
+                let cnonce = Self::generate_nonce();
+                let response = EnhancedDigestAuth::calculate_response_qop(
+                    &ha1,
+                    &self.nonce,
+                    &nc,
+                    &cnonce,
+                    qop,
+                    &ha2,
+                    algorithm,
+                );
+                value.push_str(&format!(r#", qop={}, nc={}, cnonce="{}""#, qop, nc, cnonce));
+                response
+            }
+            None => EnhancedDigestAuth::calculate_response(&ha1, &self.nonce, &ha2, algorithm),
+        };
+        value.push_str(&format!(r#", response="{}""#, response));
+        if let Some(opaque) = &self.opaque {
+            value.push_str(&format!(r#", opaque="{}""#, opaque));
+        }
+        value
+    }
+
     /// Generate a random nonce
     fn generate_nonce() -> String {
         let mut rng = rand::thread_rng();
@@ -92,8 +163,8 @@ impl AuthorizationHeader {
             .headers()
             .iter()
             .find_map(|h| match h {
-                Header::Authorization(auth) => Some(auth.to_string()),
-                Header::ProxyAuthorization(auth) => Some(auth.to_string()),
+                Header::Authorization(auth) => Some(auth.value().to_string()),
+                Header::ProxyAuthorization(auth) => Some(auth.value().to_string()),
                 _ => None,
             })
             .ok_or_else(|| SipError::Authentication("No Authorization header found".to_string()))?;
@@ -359,6 +430,35 @@ mod tests {
         assert_eq!(params.get("nonce").unwrap(), "abc123");
     }
 
+    #[test]
+    fn test_answer_upstream_challenge() {
+        let challenge = AuthChallenge::parse(
+            r#"Digest realm="provider.example", nonce="n1", algorithm=MD5, qop="auth", opaque="o1", stale=TRUE"#,
+        )
+        .unwrap();
+        assert_eq!(challenge.realm, "provider.example");
+        assert_eq!(challenge.qop.as_deref(), Some("auth"));
+        assert!(challenge.stale);
+
+        let value = challenge.authorize("pbx", "secret", "REGISTER", "sip:provider.example", 1);
+        let params = AuthorizationHeader::parse_digest_params(&value).unwrap();
+        let auth = DigestAuth::new("provider.example");
+        let expected = auth.calculate_response(
+            "pbx",
+            "secret",
+            "provider.example",
+            "n1",
+            "REGISTER",
+            "sip:provider.example",
+            Some("auth"),
+            Some("00000001"),
+            params.get("cnonce").map(|s| s.as_str()),
+        );
+        assert_eq!(params.get("response"), Some(&expected));
+        assert_eq!(params.get("nc").map(|s| s.as_str()), Some("00000001"));
+        assert_eq!(params.get("opaque").map(|s| s.as_str()), Some("o1"));
+    }
+
     #[test]
     fn test_calculate_response() {
         let auth = DigestAuth::new("test.com");
@@ -3,17 +3,98 @@
 use super::auth::{AuthChallenge, AuthorizationHeader, SipAuthenticator};
 use super::message::{SipError, SipRequest};
 use async_trait::async_trait;
+use crate::domain::device_enrollment::DeviceEnrollmentRepository;
 use crate::domain::user::UserRepository;
+use crate::infrastructure::persistence::health::{classify_db_error, DbErrorClass, DbHealth};
+use metrics::counter;
 use std::collections::HashMap;
-use std::sync::Arc;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
 use tokio::sync::RwLock;
 use tracing::{debug, info, warn};
 
+/// Bounded TTL cache of recently verified HA1 hashes
+///
+/// Only consulted while the database is unavailable, so registered phones can
+/// keep authenticating through a short failover.
+pub struct Ha1Cache {
+    ttl: Duration,
+    capacity: usize,
+    entries: Mutex<HashMap<String, (String, Instant)>>,
+}
+
+impl Ha1Cache {
+    /// Create a new cache
+    pub fn new(ttl: Duration, capacity: usize) -> Self {
+        Self {
+            ttl,
+            capacity: capacity.max(1),
+            entries: Mutex::new(HashMap::new()),
+        }
+    }
+
+    fn key(username: &str, realm: &str) -> String {
+        format!("{}@{}", username, realm)
+    }
+
+    /// Remember an HA1 that was just verified against the database
+    pub fn insert(&self, username: &str, realm: &str, ha1: &str) {
+        let mut entries = self.entries.lock().unwrap();
+        let key = Self::key(username, realm);
+
+        if !entries.contains_key(&key) && entries.len() >= self.capacity {
+            // Evict the least recently verified entry
+            if let Some(oldest) = entries
+                .iter()
+                .min_by_key(|(_, (_, verified_at))| *verified_at)
+                .map(|(k, _)| k.clone())
+            {
+                entries.remove(&oldest);
+            }
+        }
+
+        entries.insert(key, (ha1.to_string(), Instant::now()));
+    }
+
+    /// Get a cached HA1 if it has not expired
+    pub fn get(&self, username: &str, realm: &str) -> Option<String> {
+        let mut entries = self.entries.lock().unwrap();
+        let key = Self::key(username, realm);
+
+        match entries.get(&key) {
+            Some((ha1, verified_at)) if verified_at.elapsed() < self.ttl => Some(ha1.clone()),
+            Some(_) => {
+                entries.remove(&key);
+                None
+            }
+            None => None,
+        }
+    }
+
+    /// Drop a user's cached HA1 (e.g. after a password change)
+    pub fn invalidate(&self, username: &str, realm: &str) {
+        self.entries.lock().unwrap().remove(&Self::key(username, realm));
+    }
+
+    /// Number of cached entries
+    pub fn len(&self) -> usize {
+        self.entries.lock().unwrap().len()
+    }
+
+    /// Whether the cache is empty
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
 /// Digest authentication manager with database backend
 pub struct DigestAuthDb {
     realm: String,
     user_repository: Arc<dyn UserRepository>,
     active_nonces: Arc<RwLock<HashMap<String, std::time::Instant>>>,
+    ha1_cache: Option<Arc<Ha1Cache>>,
+    db_health: Option<Arc<DbHealth>>,
+    device_credentials: Option<Arc<dyn DeviceEnrollmentRepository>>,
 }
 
 impl DigestAuthDb {
@@ -23,9 +104,99 @@ impl DigestAuthDb {
             realm,
             user_repository,
             active_nonces: Arc::new(RwLock::new(HashMap::new())),
+            ha1_cache: None,
+            db_health: None,
+            device_credentials: None,
         }
     }
 
+    /// Fall back to cached HA1s while the database is unavailable
+    pub fn with_ha1_cache(mut self, cache: Arc<Ha1Cache>) -> Self {
+        self.ha1_cache = Some(cache);
+        self
+    }
+
+    /// Report database failures to, and consult, the shared health state
+    pub fn with_db_health(mut self, health: Arc<DbHealth>) -> Self {
+        self.db_health = Some(health);
+        self
+    }
+
+    /// Also accept the passwords of the users' enrolled devices
+    pub fn with_device_credentials(mut self, repository: Arc<dyn DeviceEnrollmentRepository>) -> Self {
+        self.device_credentials = Some(repository);
+        self
+    }
+
+    fn is_degraded(&self) -> bool {
+        self.db_health.as_ref().map(|h| h.is_degraded()).unwrap_or(false)
+    }
+
+    fn cached_ha1(&self, username: &str, realm: &str) -> Option<String> {
+        self.ha1_cache.as_ref().and_then(|c| c.get(username, realm))
+    }
+
+    /// Look up the HA1 for a user, falling back to the cache on failover
+    ///
+    /// Returns the HA1 and whether it came from the degraded-mode cache.
+    async fn lookup_ha1(&self, username: &str, realm: &str) -> Result<(String, bool), SipError> {
+        // Skip the pool entirely while degraded so phones are not left
+        // waiting on acquire timeouts
+        if self.is_degraded() {
+            if let Some(ha1) = self.cached_ha1(username, realm) {
+                debug!("Degraded mode: using cached HA1 for user {}", username);
+                return Ok((ha1, true));
+            }
+        }
+
+        let user = match self
+            .user_repository
+            .find_by_username_and_realm(username, realm)
+            .await
+        {
+            Ok(user) => user,
+            Err(e) => {
+                let message = e.to_string();
+                warn!("Database error while looking up user {}: {}", username, message);
+
+                if classify_db_error(&message) == DbErrorClass::Transient {
+                    if let Some(health) = &self.db_health {
+                        health.report_transient(&message);
+                    }
+                    if let Some(ha1) = self.cached_ha1(username, realm) {
+                        warn!("Database unavailable, using cached HA1 for user {}", username);
+                        return Ok((ha1, true));
+                    }
+                }
+
+                return Err(SipError::Internal(format!("Database error: {}", message)));
+            }
+        };
+
+        let user = user.ok_or_else(|| {
+            warn!("Authentication failed: unknown user {}", username);
+            SipError::Authentication(format!("Unknown user: {}", username))
+        })?;
+
+        // Check if user is enabled
+        if !user.is_enabled() {
+            warn!("User {} is disabled", username);
+            if let Some(cache) = &self.ha1_cache {
+                cache.invalidate(username, realm);
+            }
+            return Err(SipError::Authentication("User is disabled".to_string()));
+        }
+
+        // Get SIP HA1 from user record
+        let ha1 = user.sip_ha1.ok_or_else(|| {
+            warn!("User {} has no SIP HA1 hash stored", username);
+            SipError::Internal("SIP HA1 not configured for user".to_string())
+        })?;
+
+        debug!("Using stored SIP HA1 for user {}", username);
+        Ok((ha1, false))
+    }
+
     /// Generate an authentication challenge
     pub async fn create_challenge(&self) -> AuthChallenge {
         let challenge = AuthChallenge::new(&self.realm);
@@ -66,33 +237,7 @@ impl DigestAuthDb {
             return Err(SipError::Authentication("Realm mismatch".to_string()));
         }
 
-        // Get user from database
-        let user = self
-            .user_repository
-            .find_by_username_and_realm(&auth.username, &auth.realm)
-            .await
-            .map_err(|e| {
-                warn!("Database error while looking up user {}: {}", auth.username, e);
-                SipError::Internal(format!("Database error: {}", e))
-            })?
-            .ok_or_else(|| {
-                warn!("Authentication failed: unknown user {}", auth.username);
-                SipError::Authentication(format!("Unknown user: {}", auth.username))
-            })?;
-
-        // Check if user is enabled
-        if !user.is_enabled() {
-            warn!("User {} is disabled", auth.username);
-            return Err(SipError::Authentication("User is disabled".to_string()));
-        }
-
-        // Get SIP HA1 from user record
-        let ha1 = user.sip_ha1.ok_or_else(|| {
-            warn!("User {} has no SIP HA1 hash stored", auth.username);
-            SipError::Internal("SIP HA1 not configured for user".to_string())
-        })?;
-
-        debug!("Using stored SIP HA1 for user {}", auth.username);
+        let (ha1, from_cache) = self.lookup_ha1(&auth.username, &auth.realm).await?;
 
         let expected_response = Self::calculate_response_from_ha1(
             &ha1,
@@ -104,8 +249,11 @@ impl DigestAuthDb {
             auth.cnonce.as_deref(),
         );
 
-        // Verify response
-        if auth.response != expected_response {
+        // Verify response; enrolled devices have their own passwords, which
+        // are not cached
+        let verified = auth.response == expected_response
+            || (!from_cache && self.matches_device_credential(&auth, method).await?);
+        if !verified {
             warn!(
                 "Authentication failed for user {}: response mismatch",
                 auth.username
@@ -113,10 +261,53 @@ impl DigestAuthDb {
             return Err(SipError::Authentication("Invalid credentials".to_string()));
         }
 
+        if from_cache {
+            counter!("sip_auth_degraded_cache_hits_total").increment(1);
+        } else if let Some(cache) = &self.ha1_cache {
+            cache.insert(&auth.username, &auth.realm, &ha1);
+        }
+
         info!("Authentication successful for user: {}", auth.username);
         Ok(auth.username)
     }
 
+    /// Whether the response was computed with an active device credential
+    /// of the user
+    async fn matches_device_credential(
+        &self,
+        auth: &AuthorizationHeader,
+        method: &str,
+    ) -> Result<bool, SipError> {
+        let Some(devices) = &self.device_credentials else {
+            return Ok(false);
+        };
+        let user = self
+            .user_repository
+            .find_by_username_and_realm(&auth.username, &auth.realm)
+            .await
+            .map_err(|e| SipError::Internal(format!("Database error: {}", e)))?;
+        let Some(user) = user else {
+            return Ok(false);
+        };
+        let credentials = devices.list_credentials(user.id).await.map_err(SipError::Internal)?;
+
+        let matched = credentials.iter().filter(|c| c.is_active()).find(|c| {
+            Self::calculate_response_from_ha1(
+                &c.sip_ha1,
+                &auth.nonce,
+                method,
+                &auth.uri,
+                auth.qop.as_deref(),
+                auth.nc.as_deref(),
+                auth.cnonce.as_deref(),
+            ) == auth.response
+        });
+        if let Some(credential) = matched {
+            debug!("User {} authenticated with device {}", auth.username, credential.id);
+        }
+        Ok(matched.is_some())
+    }
+
     /// Calculate digest response from HA1
     fn calculate_response_from_ha1(
         ha1: &str,
@@ -168,3 +359,318 @@ impl SipAuthenticator for DigestAuthDb {
         self.verify_request(request, method).await
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::domain::device_enrollment::{
+        CreateEnrollment, EnrollmentConfig, EnrollmentError, EnrollmentService,
+    };
+    use crate::domain::shared::error::{DomainError, Result as DomainResult};
+    use crate::infrastructure::persistence::MemoryDeviceEnrollmentRepository;
+    use crate::domain::shared::SortOrder;
+    use crate::domain::user::{ChangePassword, CreateUser, DirectoryQuery, UpdateUser, User};
+    use chrono::Utc;
+    use std::sync::atomic::{AtomicBool, Ordering};
+
+    const REALM: &str = "test.com";
+
+    /// User repository that can be switched into a simulated failover
+    struct FlakyUserRepository {
+        user: User,
+        failing: AtomicBool,
+    }
+
+    impl FlakyUserRepository {
+        fn new(username: &str, password: &str) -> Self {
+            let ha1 = format!("{:x}", md5::compute(format!("{}:{}:{}", username, REALM, password)));
+            Self {
+                user: User {
+                    id: 1,
+                    username: username.to_string(),
+                    password_hash: String::new(),
+                    sip_ha1: Some(ha1),
+                    realm: REALM.to_string(),
+                    display_name: None,
+                    email: None,
+                    department: None,
+                    locale: None,
+                    timezone: None,
+                    enabled: true,
+                    role_id: None,
+                    created_at: Utc::now(),
+                    updated_at: Utc::now(),
+                },
+                failing: AtomicBool::new(false),
+            }
+        }
+
+        fn check(&self) -> DomainResult<()> {
+            if self.failing.load(Ordering::SeqCst) {
+                Err(DomainError::Internal(
+                    "Failed to find user: pool timed out while waiting for an open connection"
+                        .to_string(),
+                ))
+            } else {
+                Ok(())
+            }
+        }
+    }
+
+    #[async_trait]
+    impl UserRepository for FlakyUserRepository {
+        async fn create(&self, _data: CreateUser) -> DomainResult<User> {
+            self.check()?;
+            Ok(self.user.clone())
+        }
+
+        async fn find_by_id(&self, id: i32) -> DomainResult<Option<User>> {
+            self.check()?;
+            Ok(Some(self.user.clone()).filter(|u| u.id == id))
+        }
+
+        async fn find_by_username(&self, username: &str) -> DomainResult<Option<User>> {
+            self.check()?;
+            Ok(Some(self.user.clone()).filter(|u| u.username == username))
+        }
+
+        async fn find_by_username_and_realm(
+            &self,
+            username: &str,
+            realm: &str,
+        ) -> DomainResult<Option<User>> {
+            self.check()?;
+            Ok(Some(self.user.clone()).filter(|u| u.username == username && u.realm == realm))
+        }
+
+        async fn list(&self, _sort: &SortOrder, _limit: i64, _offset: i64) -> DomainResult<Vec<User>> {
+            self.check()?;
+            Ok(vec![self.user.clone()])
+        }
+
+        async fn list_by_realm(
+            &self,
+            _realm: &str,
+            _sort: &SortOrder,
+            _limit: i64,
+            _offset: i64,
+        ) -> DomainResult<Vec<User>> {
+            self.check()?;
+            Ok(vec![self.user.clone()])
+        }
+
+        async fn update(&self, _id: i32, _data: UpdateUser) -> DomainResult<User> {
+            self.check()?;
+            Ok(self.user.clone())
+        }
+
+        async fn change_password(&self, _id: i32, _data: ChangePassword) -> DomainResult<()> {
+            self.check()
+        }
+
+        async fn delete(&self, _id: i32) -> DomainResult<()> {
+            self.check()
+        }
+
+        async fn set_enabled(&self, _id: i32, _enabled: bool) -> DomainResult<()> {
+            self.check()
+        }
+
+        async fn count(&self) -> DomainResult<i64> {
+            self.check()?;
+            Ok(1)
+        }
+
+        async fn count_by_realm(&self, _realm: &str) -> DomainResult<i64> {
+            self.check()?;
+            Ok(1)
+        }
+
+        async fn search(&self, query: &DirectoryQuery) -> DomainResult<Vec<User>> {
+            self.check()?;
+            Ok(Some(self.user.clone()).filter(|u| query.matches(u)).into_iter().collect())
+        }
+
+        async fn verify_credentials(&self, _username: &str, _password: &str) -> DomainResult<Option<User>> {
+            self.check()?;
+            Ok(None)
+        }
+    }
+
+    fn authorized_request(method: &str, username: &str, password: &str, nonce: &str) -> SipRequest {
+        let uri = format!("sip:{}", REALM);
+        let ha1 = format!("{:x}", md5::compute(format!("{}:{}:{}", username, REALM, password)));
+        let response =
+            DigestAuthDb::calculate_response_from_ha1(&ha1, nonce, method, &uri, None, None, None);
+
+        let raw = format!(
+            "{method} {uri} SIP/2.0\r\n\
+             Via: SIP/2.0/UDP 127.0.0.1:5070;branch=z9hG4bK-{nonce}\r\n\
+             From: <sip:{username}@{REALM}>;tag=1\r\n\
+             To: <sip:{username}@{REALM}>\r\n\
+             Call-ID: {nonce}@127.0.0.1\r\n\
+             CSeq: 1 {method}\r\n\
+             Authorization: Digest username=\"{username}\", realm=\"{REALM}\", nonce=\"{nonce}\", uri=\"{uri}\", response=\"{response}\"\r\n\
+             Content-Length: 0\r\n\r\n"
+        );
+        SipRequest::parse(raw.as_bytes()).unwrap()
+    }
+
+    async fn authenticate(auth: &DigestAuthDb, method: &str, password: &str) -> Result<String, SipError> {
+        let challenge = auth.create_challenge().await;
+        let request = authorized_request(method, "alice", password, &challenge.nonce);
+        auth.verify_request(&request, method).await
+    }
+
+    #[test]
+    fn test_ha1_cache_ttl_and_capacity() {
+        let cache = Ha1Cache::new(Duration::from_secs(60), 2);
+        cache.insert("alice", REALM, "a");
+        cache.insert("bob", REALM, "b");
+        cache.insert("carol", REALM, "c");
+
+        assert_eq!(cache.len(), 2);
+        assert!(cache.get("alice", REALM).is_none());
+        assert_eq!(cache.get("carol", REALM).as_deref(), Some("c"));
+
+        let expired = Ha1Cache::new(Duration::ZERO, 2);
+        expired.insert("alice", REALM, "a");
+        assert!(expired.get("alice", REALM).is_none());
+    }
+
+    #[tokio::test]
+    async fn test_registered_user_authenticates_during_outage() {
+        let repo = Arc::new(FlakyUserRepository::new("alice", "secret"));
+        let health = Arc::new(DbHealth::default());
+        let auth = DigestAuthDb::new(REALM.to_string(), repo.clone())
+            .with_ha1_cache(Arc::new(Ha1Cache::new(Duration::from_secs(3600), 100)))
+            .with_db_health(health.clone());
+
+        // REGISTER while the database is healthy populates the cache
+        assert_eq!(authenticate(&auth, "REGISTER", "secret").await.unwrap(), "alice");
+
+        // Failover: the repository now returns transient errors
+        repo.failing.store(true, Ordering::SeqCst);
+
+        assert_eq!(authenticate(&auth, "INVITE", "secret").await.unwrap(), "alice");
+        assert!(health.is_degraded());
+
+        // Wrong credentials are still rejected from the cache
+        assert!(matches!(
+            authenticate(&auth, "INVITE", "wrong").await,
+            Err(SipError::Authentication(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_outage_without_cache_fails() {
+        let repo = Arc::new(FlakyUserRepository::new("alice", "secret"));
+        let auth = DigestAuthDb::new(REALM.to_string(), repo.clone());
+
+        assert!(authenticate(&auth, "REGISTER", "secret").await.is_ok());
+
+        repo.failing.store(true, Ordering::SeqCst);
+        assert!(matches!(
+            authenticate(&auth, "INVITE", "secret").await,
+            Err(SipError::Internal(_))
+        ));
+    }
+
+    fn enrollment_service(users: Arc<FlakyUserRepository>) -> (EnrollmentService, Arc<MemoryDeviceEnrollmentRepository>) {
+        let devices = Arc::new(MemoryDeviceEnrollmentRepository::new());
+        let service = EnrollmentService::new(devices.clone(), users, EnrollmentConfig::default())
+            .with_sip_server(REALM.to_string(), 5060);
+        (service, devices)
+    }
+
+    #[tokio::test]
+    async fn test_enrolled_device_registers_until_revoked() {
+        let users = Arc::new(FlakyUserRepository::new("alice", "secret"));
+        let (service, devices) = enrollment_service(users.clone());
+        let auth = DigestAuthDb::new(REALM.to_string(), users).with_device_credentials(devices);
+
+        let issued = service.create(1, CreateEnrollment::default()).await.unwrap();
+        let bundle = service.redeem(&issued.token, "198.51.100.7").await.unwrap();
+
+        assert_eq!(authenticate(&auth, "REGISTER", &bundle.password).await.unwrap(), "alice");
+        // The user's own password keeps working
+        assert!(authenticate(&auth, "REGISTER", "secret").await.is_ok());
+
+        service.revoke(1, bundle.device_id).await.unwrap();
+        assert!(matches!(
+            authenticate(&auth, "REGISTER", &bundle.password).await,
+            Err(SipError::Authentication(_))
+        ));
+        assert!(authenticate(&auth, "REGISTER", "secret").await.is_ok());
+        assert_eq!(
+            service.revoke(1, bundle.device_id).await,
+            Err(EnrollmentError::DeviceNotFound(bundle.device_id))
+        );
+    }
+
+    #[tokio::test]
+    async fn test_enrollment_token_single_use_and_expiry() {
+        let users = Arc::new(FlakyUserRepository::new("alice", "secret"));
+        let (service, _) = enrollment_service(users);
+        let now = Utc::now();
+
+        let issued = service.create_at(1, CreateEnrollment::default(), now).await.unwrap();
+        assert!(service.redeem_at(&issued.token, "a", now).await.is_ok());
+        assert_eq!(
+            service.redeem_at(&issued.token, "a", now).await.unwrap_err(),
+            EnrollmentError::InvalidToken
+        );
+
+        let request = CreateEnrollment {
+            ttl_secs: Some(60),
+            ..Default::default()
+        };
+        let issued = service.create_at(1, request, now).await.unwrap();
+        assert_eq!(
+            service
+                .redeem_at(&issued.token, "b", now + chrono::Duration::seconds(61))
+                .await
+                .unwrap_err(),
+            EnrollmentError::InvalidToken
+        );
+        assert!(service.redeem_at(&issued.token, "b", now).await.is_ok());
+
+        // Guessing tokens is rate limited per client
+        for _ in 0..8 {
+            let _ = service.redeem_at("guess", "b", now).await;
+        }
+        assert_eq!(
+            service.redeem_at("guess", "b", now).await.unwrap_err(),
+            EnrollmentError::RateLimited
+        );
+        assert_eq!(
+            service
+                .redeem_at("guess", "b", now + chrono::Duration::minutes(3))
+                .await
+                .unwrap_err(),
+            EnrollmentError::InvalidToken
+        );
+    }
+
+    #[tokio::test]
+    async fn test_enrollment_bundle_omits_main_password() {
+        let users = Arc::new(FlakyUserRepository::new("alice", "secret"));
+        let main_ha1 = users.user.sip_ha1.clone().unwrap();
+        let (service, _) = enrollment_service(users);
+
+        let issued = service.create(1, CreateEnrollment::default()).await.unwrap();
+        assert!(issued.qr_payload.contains(&issued.url));
+        let bundle = service.redeem(&issued.token, "198.51.100.7").await.unwrap();
+
+        assert_eq!(bundle.username, "alice");
+        assert_eq!(bundle.server, REALM);
+        assert_ne!(bundle.password, "secret");
+        let json = serde_json::to_string(&bundle).unwrap();
+        assert!(!json.contains("\"secret\""));
+        assert!(!json.contains(&main_ha1));
+
+        let devices = service.devices(1).await.unwrap();
+        assert_eq!(devices.len(), 1);
+        assert!(!serde_json::to_string(&devices).unwrap().contains("sip_ha1"));
+    }
+}
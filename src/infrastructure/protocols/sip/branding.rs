@@ -0,0 +1,142 @@
+//! Tenant branding of requests leaving toward trunks
+//!
+//! The From display name is replaced by the tenant's template and the
+//! tenant's custom header is added (replacing any copy the caller sent).
+
+use super::message::{SipError, SipRequest};
+use crate::domain::tenant_branding::TenantBranding;
+
+/// Copy of `request` carrying the tenant's display name and header
+pub fn brand_request(
+    request: &SipRequest,
+    branding: &TenantBranding,
+) -> Result<SipRequest, SipError> {
+    if branding.display_name.is_none() && branding.header.is_none() {
+        return Ok(request.clone());
+    }
+
+    let text = String::from_utf8_lossy(&request.to_bytes()).to_string();
+    let (head, body) = text.split_once("\r\n\r\n").unwrap_or((&text, ""));
+    let mut lines: Vec<String> = Vec::new();
+    for line in head.split("\r\n") {
+        let name = line.split_once(':').map(|(name, _)| name.trim());
+        match name {
+            Some(name) if name.eq_ignore_ascii_case("From") || name.eq_ignore_ascii_case("f") => {
+                let value = line
+                    .split_once(':')
+                    .map(|(_, v)| v.trim())
+                    .unwrap_or_default();
+                lines.push(format!("From: {}", brand_from(value, branding)));
+            }
+            Some(name)
+                if branding
+                    .header
+                    .as_ref()
+                    .is_some_and(|h| h.name.eq_ignore_ascii_case(name)) => {}
+            _ => lines.push(line.to_string()),
+        }
+    }
+    if let Some(header) = &branding.header {
+        lines.push(format!("{}: {}", header.name, header.value));
+    }
+
+    SipRequest::parse(format!("{}\r\n\r\n{}", lines.join("\r\n"), body).as_bytes())
+}
+
+/// From header value with the tenant's display name
+fn brand_from(value: &str, branding: &TenantBranding) -> String {
+    // name-addr ("Name" <uri>;params) or addr-spec (uri;params)
+    let (display, uri, params) = match (value.find('<'), value.find('>')) {
+        (Some(start), Some(end)) if start < end => (
+            value[..start].trim().trim_matches('"'),
+            &value[start + 1..end],
+            &value[end + 1..],
+        ),
+        _ => match value.find(';') {
+            Some(semi) => ("", &value[..semi], &value[semi..]),
+            None => ("", value, ""),
+        },
+    };
+    let user = uri
+        .trim_start_matches("sips:")
+        .trim_start_matches("sip:")
+        .split('@')
+        .next()
+        .unwrap_or_default();
+    let display = (!display.is_empty()).then_some(display);
+
+    match branding.display_name_for(user, display) {
+        Some(name) => format!("\"{}\" <{}>{}", name, uri, params),
+        None => value.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::domain::tenant_branding::BrandingHeader;
+
+    fn invite(from: &str) -> SipRequest {
+        let text = format!(
+            "INVITE sip:+15551234@trunk.example.net SIP/2.0\r\n\
+             Via: SIP/2.0/UDP 192.0.2.1:5060;branch=z9hG4bK1\r\n\
+             From: {}\r\n\
+             To: <sip:+15551234@trunk.example.net>\r\n\
+             Call-ID: branding-test\r\n\
+             CSeq: 1 INVITE\r\n\
+             X-Company: Spoofed\r\n\
+             Content-Length: 0\r\n\r\n",
+            from
+        );
+        SipRequest::parse(text.as_bytes()).unwrap()
+    }
+
+    fn header(request: &SipRequest, name: &str) -> Vec<String> {
+        String::from_utf8_lossy(&request.to_bytes())
+            .split("\r\n")
+            .filter_map(|line| line.split_once(": "))
+            .filter(|(n, _)| n.eq_ignore_ascii_case(name))
+            .map(|(_, v)| v.to_string())
+            .collect()
+    }
+
+    fn acme() -> TenantBranding {
+        TenantBranding {
+            display_name: Some("Acme - {display}".to_string()),
+            header: Some(BrandingHeader {
+                name: "X-Company".to_string(),
+                value: "Acme Corp".to_string(),
+            }),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_display_name_and_header() {
+        let branded = brand_request(
+            &invite("\"Alice\" <sip:alice@acme.example.com>;tag=a1"),
+            &acme(),
+        )
+        .unwrap();
+
+        assert_eq!(
+            header(&branded, "From"),
+            vec!["\"Acme - Alice\" <sip:alice@acme.example.com>;tag=a1"]
+        );
+        assert_eq!(header(&branded, "X-Company"), vec!["Acme Corp"]);
+        assert_eq!(header(&branded, "Call-ID"), vec!["branding-test"]);
+    }
+
+    #[test]
+    fn test_addr_spec_from_and_unbranded_tenant() {
+        let branded = brand_request(&invite("sip:1001@acme.example.com;tag=a1"), &acme()).unwrap();
+        assert_eq!(
+            header(&branded, "From"),
+            vec!["\"Acme - 1001\" <sip:1001@acme.example.com>;tag=a1"]
+        );
+
+        let request = invite("<sip:1001@acme.example.com>;tag=a1");
+        let unbranded = brand_request(&request, &TenantBranding::default()).unwrap();
+        assert_eq!(unbranded.to_bytes(), request.to_bytes());
+    }
+}
@@ -1,13 +1,18 @@
 //! SIP message builder utilities (Simplified version)
 
 use super::message::{SipError, SipRequest, SipResponse};
+use super::quirks::QuirksRegistry;
+use rsip::headers::UntypedHeader;
 use rsip::{Header, Headers, Response, StatusCode, Version};
+use std::sync::Arc;
 
 /// Build a simple SIP response from a request
 pub struct ResponseBuilder {
     status_code: u16,
     headers: Vec<Header>,
     body: Vec<u8>,
+    to_tag: Option<String>,
+    quirks: Option<Arc<QuirksRegistry>>,
 }
 
 impl ResponseBuilder {
@@ -16,6 +21,8 @@ impl ResponseBuilder {
             status_code,
             headers: Vec::new(),
             body: Vec::new(),
+            to_tag: None,
+            quirks: None,
         }
     }
 
@@ -41,10 +48,27 @@ impl ResponseBuilder {
         self
     }
 
+    /// Add a To tag when the request has none (dialog-creating responses)
+    pub fn to_tag(mut self, tag: &str) -> Self {
+        self.to_tag = Some(tag.to_string());
+        self
+    }
+
+    /// Apply the requesting device's response quirks when building
+    pub fn quirks(mut self, quirks: Arc<QuirksRegistry>) -> Self {
+        self.quirks = Some(quirks);
+        self
+    }
+
     pub fn build_for_request(mut self, request: &SipRequest) -> Result<SipResponse, SipError> {
         // Copy essential headers from request
         for header in request.headers().iter() {
             match header {
+                Header::To(to) if self.to_tag.is_some() && !to.value().contains("tag=") => {
+                    let tag = self.to_tag.as_deref().unwrap_or_default();
+                    self.headers
+                        .push(Header::To(format!("{};tag={}", to.value(), tag).into()));
+                }
                 Header::Via(_) | Header::From(_) | Header::To(_) | Header::CallId(_) | Header::CSeq(_) => {
                     self.headers.push(header.clone());
                 }
@@ -68,7 +92,11 @@ impl ResponseBuilder {
             version: Version::V2,
         };
 
-        Ok(SipResponse::new(response))
+        let mut response = SipResponse::new(response);
+        if let Some(quirks) = &self.quirks {
+            quirks.apply_to_response(request, &mut response);
+        }
+        Ok(response)
     }
 }
 
@@ -1,22 +1,75 @@
 //! Call handling (INVITE, ACK, BYE)
 
+use super::address::{unspecified_like, uri_socket_addr};
+use super::advertise::{peer_ip, AddressAdvertiser};
 use super::auth::SipAuthenticator;
 use super::builder::ResponseBuilder;
-use super::call_router::CallRouter;
-use super::handler::SipHandler;
+use super::call_router::{CallRouter, TakeoverRequest};
+use super::call_state::{CallLeg, CallState};
+use super::dialog::ReinviteAction;
+use super::handler::{SipHandler, TransactionAction, TransactionHandle, TransactionUser};
+use super::header_rules::HeaderRulesEngine;
 use super::hold_manager::SdpHoldHelper;
+use super::hold_supervisor::NoTransferor;
+use super::hops::HopTracker;
+use super::maintenance::{MaintenanceRegistry, ANNOUNCEMENT_SECS as MAINTENANCE_ANNOUNCEMENT_SECS};
+use super::internal_services::{InternalService, InternalServiceHandler};
 use super::message::{SipError, SipMethod, SipRequest, SipResponse};
+use super::overload::OverloadMonitor;
+use super::redirect::{InviteForwarder, RedirectPolicy};
 use super::registrar::Registrar;
+use super::replaces::{LegReleaser, Replaces, TakeoverPolicy};
+use super::resolver::SipResolver;
+use super::rport::extract_received_from_via;
 use super::sdp::SdpSession;
+use super::survey::SurveyRunner;
+use super::survivability::{
+    DeferredItem, SurvivabilityManager, SurvivableRoute, ANNOUNCEMENT_SECS as SURVIVABILITY_ANNOUNCEMENT_SECS,
+};
+use super::trunk_manager::TrunkManager;
+use super::transfer::{refer_to_uri, TransferNotifier, TransferPolicy};
+use crate::application::call::CallApplicationService;
+use crate::domain::call_announcer::{AnnouncementRequest, AnnouncementType, CallAnnouncer};
+use crate::domain::call_recording::CallRecordingManager;
+use crate::domain::call_screening::ScreeningService;
 use crate::domain::cdr::CdrRepository;
-use crate::infrastructure::media::{CodecNegotiator, MediaBridge, MediaStream, StreamDirection};
+use crate::domain::class_of_service::{
+    ClassCheck, ClassOfServiceRegistry, DestinationCategory,
+    ANNOUNCEMENT_SECS as COS_ANNOUNCEMENT_SECS, MAX_CODE_DIGITS,
+};
+use crate::domain::feature_code::{
+    is_feature_code_pattern, FeatureCall, FeatureCodeRegistry, FeatureOutcome, UnknownFeatureCode,
+};
+use crate::domain::fraud_detection::{FraudDecision, FraudDetector, OutboundCall};
+use crate::domain::routing::{
+    AnnouncementService, AutoAttendantService, PlannedAnnouncement, PlannedAttendant,
+    RoutingLinter, RoutingSimulator,
+};
+use crate::domain::speed_dial::SpeedDialService;
+use crate::domain::tenant_branding::BrandingRegistry;
+use crate::infrastructure::call_debug::CallDebugRegistry;
+use crate::infrastructure::ivr::{
+    collect_digits, DtmfDispatcher, IvrDirectory, IvrFlowEngine, IvrOutcome,
+};
+use crate::infrastructure::lnp::LnpResolver;
+use crate::infrastructure::media::bandwidth::UPDATE_INTERVAL as BANDWIDTH_UPDATE_INTERVAL;
+use crate::infrastructure::media::{
+    BandwidthMonitor, CapacityMonitor, CodecNegotiator, LatchConfig, MediaBridge, MediaStream, MediaStreamGuard, MohClassRegistry, PacketFormat,
+    PayloadMap, RingbackConfig, RtpPortAllocator, StreamDirection,
+};
+use crate::infrastructure::media::rtp::{ExtMap, AUDIO_LEVEL_URI};
+use crate::infrastructure::sequence_vault::SequenceVault;
+use crate::infrastructure::setup_latency::{SetupLatencyMonitor, SetupMilestone, SetupTimeline};
 use async_trait::async_trait;
+use chrono::Utc;
 use rsip::Header;
 use std::collections::HashMap;
 use std::net::IpAddr;
 use std::sync::Arc;
+use std::time::{Duration, Instant};
 use tokio::sync::RwLock;
 use tracing::{debug, info, warn};
+use uuid::Uuid;
 
 /// Active call session
 pub struct CallSession {
@@ -40,12 +93,108 @@ pub struct InviteHandler {
     registrar: Arc<Registrar>,
     pub active_calls: Arc<RwLock<HashMap<String, CallSession>>>,
     local_ip: IpAddr,
+    /// Media address for calls whose offer is IPv6
+    local_ipv6: Option<IpAddr>,
+    /// External address put in SDP when running behind NAT
+    advertiser: Option<Arc<AddressAdvertiser>>,
     auth: Option<Arc<dyn SipAuthenticator>>,
     codec_negotiator: CodecNegotiator,
-    next_rtp_port: Arc<RwLock<u16>>,
+    /// Offer the client-to-mixer audio level header extension in SDP
+    audio_level_extension: bool,
+    /// RTP port pairs for call media
+    port_allocator: Arc<RtpPortAllocator>,
     call_router: Arc<CallRouter>,
     /// Enable auto-answer mode (for testing/simple PBX)
     auto_answer: bool,
+    /// Star codes of PBX features, looked up before speed dials
+    feature_codes: Option<Arc<FeatureCodeRegistry>>,
+    /// Personal and company speed dials, resolved before routing
+    speed_dials: Option<Arc<SpeedDialService>>,
+    cdr_repository: Option<Arc<dyn CdrRepository>>,
+    /// Velocity rules applied to outbound calls
+    fraud_detector: Option<Arc<FraudDetector>>,
+    /// Destination categories local callers may reach
+    class_of_service: Option<Arc<ClassOfServiceRegistry>>,
+    /// Plays fraud confirmation prompts
+    call_announcer: Option<Arc<CallAnnouncer>>,
+    /// Limits on following 3xx redirects
+    redirect_policy: RedirectPolicy,
+    /// Timeout and recovery of blind transfers
+    transfer_policy: TransferPolicy,
+    /// Publishes call lifecycle events
+    call_events: Option<Arc<CallApplicationService>>,
+    /// Via branches and loop detection of forwarded INVITEs
+    hops: Option<Arc<HopTracker>>,
+    /// Tenant MOH, prompts and outbound caller identity
+    branding: Option<Arc<BrandingRegistry>>,
+    /// MOH classes selected per queue or tenant
+    moh_classes: Option<Arc<MohClassRegistry>>,
+    /// Per-trunk and per-route header rules of forwarded INVITEs
+    header_rules: Option<Arc<HeaderRulesEngine>>,
+    /// Calls and users with per-call debug logging enabled
+    call_debug: Option<Arc<CallDebugRegistry>>,
+    /// Pre-answer screening of calls to users who enabled it
+    screening: Option<Arc<ScreeningService>>,
+    /// Records answered calls when auto-record is on
+    recording: Option<Arc<CallRecordingManager>>,
+    /// Routing overrides of ported numbers
+    lnp: Option<Arc<LnpResolver>>,
+    /// Inbound and outbound channels of each trunk
+    trunks: Option<Arc<TrunkManager>>,
+    /// Skips optional work of new calls under overload
+    overload: Option<Arc<OverloadMonitor>>,
+    /// Our CSeqs and RTP SSRCs, kept across restarts
+    sequences: Option<Arc<SequenceVault>>,
+    /// Bandwidth estimation of WebRTC legs offering goog-remb
+    bandwidth: Option<Arc<BandwidthMonitor>>,
+    /// NAPTR/SRV resolution and failover of forwarded INVITEs
+    sip_resolver: Option<Arc<SipResolver>>,
+    /// Setup times of calls, checked against latency SLOs
+    setup_latency: Option<Arc<SetupLatencyMonitor>>,
+    /// Who may take over whose calls with INVITE/Replaces
+    takeover_policy: TakeoverPolicy,
+    /// Ends the dialogs of devices replaced by a takeover
+    leg_releaser: Option<Arc<dyn LegReleaser>>,
+    /// Local ringback or relayed early media while callees alert
+    ringback: RingbackConfig,
+    /// Echo, milliwatt and readback test services
+    internal_services: Option<Arc<InternalServiceHandler>>,
+    /// Tenants and trunks taken out of service
+    maintenance: Option<Arc<MaintenanceRegistry>>,
+    /// Local routing of a branch node while its upstream is unreachable
+    survivability: Option<Arc<SurvivabilityManager>>,
+    /// Announcement-only numbers, answered without a registrar lookup
+    announcements: Option<Arc<AnnouncementService>>,
+    /// Reaches the follow-up destinations of announcement-only numbers
+    forwarder: Option<Arc<dyn InviteForwarder>>,
+    /// Auto-attendants, answered without a registrar lookup
+    auto_attendants: Option<Arc<AutoAttendantService>>,
+    /// Runs the menus of auto-attendants
+    ivr: Option<Arc<IvrFlowEngine>>,
+    /// Digits of the calls, read by auto-attendant menus
+    dtmf: Option<Arc<DtmfDispatcher>>,
+}
+
+/// What follows the prompts played to an answered call
+enum AfterPrompts {
+    /// After `after_secs`, transfer the caller to `target` or hang up
+    FollowUp { after_secs: u64, target: Option<String> },
+    /// Run an auto-attendant's menu on the caller's digits
+    Attendant(PlannedAttendant),
+    /// Read an authorization code for a call its class of service does not
+    /// allow; put the call through to the dialed destination when it is
+    /// good, hang up otherwise
+    AuthorizationCode(DestinationCategory),
+}
+
+/// Local media of a call, negotiated from an INVITE's offer
+struct NegotiatedMedia {
+    stream: Arc<MediaStream>,
+    format: PacketFormat,
+    /// Codecs accepted from the offer, numbered as offered
+    payloads: PayloadMap,
+    local_ip: IpAddr,
+    local_port: u16,
 }
 
 impl InviteHandler {
@@ -55,11 +204,48 @@ impl InviteHandler {
             registrar: registrar.clone(),
             active_calls: Arc::new(RwLock::new(HashMap::new())),
             local_ip,
+            local_ipv6: None,
+            advertiser: None,
             auth: None,
             codec_negotiator: CodecNegotiator::new(),
-            next_rtp_port: Arc::new(RwLock::new(10000)),
+            audio_level_extension: false,
+            port_allocator: Arc::new(RtpPortAllocator::default()),
             call_router,
             auto_answer: true, // Default to auto-answer for backward compatibility
+            feature_codes: None,
+            speed_dials: None,
+            cdr_repository: None,
+            fraud_detector: None,
+            class_of_service: None,
+            call_announcer: None,
+            redirect_policy: RedirectPolicy::default(),
+            transfer_policy: TransferPolicy::default(),
+            call_events: None,
+            hops: None,
+            branding: None,
+            moh_classes: None,
+            header_rules: None,
+            call_debug: None,
+            screening: None,
+            recording: None,
+            lnp: None,
+            trunks: None,
+            overload: None,
+            sequences: None,
+            bandwidth: None,
+            sip_resolver: None,
+            setup_latency: None,
+            takeover_policy: TakeoverPolicy::default(),
+            leg_releaser: None,
+            ringback: RingbackConfig::default(),
+            internal_services: None,
+            maintenance: None,
+            survivability: None,
+            announcements: None,
+            forwarder: None,
+            auto_attendants: None,
+            ivr: None,
+            dtmf: None,
         }
     }
 
@@ -70,11 +256,48 @@ impl InviteHandler {
             registrar: registrar.clone(),
             active_calls: Arc::new(RwLock::new(HashMap::new())),
             local_ip,
+            local_ipv6: None,
+            advertiser: None,
             auth: Some(auth),
             codec_negotiator: CodecNegotiator::new(),
-            next_rtp_port: Arc::new(RwLock::new(10000)),
+            audio_level_extension: false,
+            port_allocator: Arc::new(RtpPortAllocator::default()),
             call_router,
             auto_answer: true,
+            feature_codes: None,
+            speed_dials: None,
+            cdr_repository: None,
+            fraud_detector: None,
+            class_of_service: None,
+            call_announcer: None,
+            redirect_policy: RedirectPolicy::default(),
+            transfer_policy: TransferPolicy::default(),
+            call_events: None,
+            hops: None,
+            branding: None,
+            moh_classes: None,
+            header_rules: None,
+            call_debug: None,
+            screening: None,
+            recording: None,
+            lnp: None,
+            trunks: None,
+            overload: None,
+            sequences: None,
+            bandwidth: None,
+            sip_resolver: None,
+            setup_latency: None,
+            takeover_policy: TakeoverPolicy::default(),
+            leg_releaser: None,
+            ringback: RingbackConfig::default(),
+            internal_services: None,
+            maintenance: None,
+            survivability: None,
+            announcements: None,
+            forwarder: None,
+            auto_attendants: None,
+            ivr: None,
+            dtmf: None,
         }
     }
 
@@ -90,118 +313,1459 @@ impl InviteHandler {
 
     /// Set CDR repository (for existing handler)
     pub fn with_cdr_repository(mut self, cdr_repository: Arc<dyn CdrRepository>) -> Self {
-        // Replace call_router with one that has CDR repository
-        let new_router = CallRouter::new(self.registrar.clone())
-            .with_cdr_repository(cdr_repository);
-        self.call_router = Arc::new(new_router);
+        self.cdr_repository = Some(cdr_repository);
+        self.rebuild_call_router();
+        self
+    }
+
+    /// Check outbound calls against fraud rules before routing
+    pub fn with_fraud_detection(mut self, fraud_detector: Arc<FraudDetector>) -> Self {
+        self.fraud_detector = Some(fraud_detector);
+        self.rebuild_call_router();
+        self
+    }
+
+    /// Check calls of local users against their class of service; callers
+    /// may get past it with an authorization code entered as DTMF
+    pub fn with_class_of_service(
+        mut self,
+        class_of_service: Arc<ClassOfServiceRegistry>,
+        dtmf: Arc<DtmfDispatcher>,
+    ) -> Self {
+        self.class_of_service = Some(class_of_service);
+        self.dtmf = Some(dtmf);
+        self
+    }
+
+    /// Announcer used for fraud confirmation prompts
+    pub fn with_call_announcer(mut self, call_announcer: Arc<CallAnnouncer>) -> Self {
+        self.call_announcer = Some(call_announcer);
+        self
+    }
+
+    /// Local IPv6 address used for media when the caller's SDP is IPv6
+    pub fn with_local_ipv6(mut self, local_ipv6: IpAddr) -> Self {
+        self.local_ipv6 = Some(local_ipv6);
+        self
+    }
+
+    /// Advertise the external address in SDP to peers outside the local subnets
+    pub fn with_address_advertiser(mut self, advertiser: Arc<AddressAdvertiser>) -> Self {
+        self.advertiser = Some(advertiser);
+        self
+    }
+
+    /// Packet times accepted from callers, in milliseconds
+    pub fn with_ptime_bounds(mut self, min_ms: u32, max_ms: u32) -> Self {
+        self.codec_negotiator = self.codec_negotiator.with_ptime_bounds(min_ms, max_ms);
+        self
+    }
+
+    /// List the audio level header extension (RFC 6464) in our SDP; it is
+    /// answered only to callers offering it
+    pub fn with_audio_level_extension(mut self, enabled: bool) -> Self {
+        self.audio_level_extension = enabled;
+        self
+    }
+
+    /// Negotiate new calls with low-bandwidth codecs while `monitor`
+    /// reports the system under load
+    pub fn with_capacity_monitor(mut self, monitor: Arc<CapacityMonitor>) -> Self {
+        self.codec_negotiator = self.codec_negotiator.with_capacity_monitor(monitor);
+        self
+    }
+
+    /// Allocator for RTP port pairs
+    pub fn with_port_allocator(mut self, port_allocator: Arc<RtpPortAllocator>) -> Self {
+        self.port_allocator = port_allocator;
+        self
+    }
+
+    /// Local media address in the family of the offer's audio connection
+    fn media_ip(&self, offer: Option<&SdpSession>) -> IpAddr {
+        match (offer.and_then(|o| o.audio_address()), self.local_ipv6) {
+            (Some(IpAddr::V6(_)), Some(local_ipv6)) => local_ipv6,
+            _ => self.local_ip,
+        }
+    }
+
+    /// Address put in the SDP for `media_ip`, as seen by the sender of `request`
+    fn advertised_media_ip(&self, media_ip: IpAddr, request: &SipRequest) -> IpAddr {
+        match &self.advertiser {
+            Some(advertiser) => advertiser.advertise(media_ip, peer_ip(request)),
+            None => media_ip,
+        }
+    }
+
+    /// Limits on following 3xx redirects of forwarded calls
+    pub fn with_redirect_policy(mut self, redirect_policy: RedirectPolicy) -> Self {
+        self.redirect_policy = redirect_policy;
+        self.rebuild_call_router();
+        self
+    }
+
+    /// Timeout and recovery destination of blind transfers
+    pub fn with_transfer_policy(mut self, transfer_policy: TransferPolicy) -> Self {
+        self.transfer_policy = transfer_policy;
+        self.rebuild_call_router();
+        self
+    }
+
+    /// Publish call lifecycle events (CDR updates, WebSocket, audit)
+    pub fn with_call_events(mut self, call_events: Arc<CallApplicationService>) -> Self {
+        self.call_events = Some(call_events);
+        self.rebuild_call_router();
+        self
+    }
+
+    /// Forward INVITEs with our own Via and branch
+    pub fn with_hop_tracker(mut self, hops: Arc<HopTracker>) -> Self {
+        self.hops = Some(hops);
+        self.rebuild_call_router();
+        self
+    }
+
+    /// Play and send calls with their tenant's branding
+    pub fn with_branding(mut self, branding: Arc<BrandingRegistry>) -> Self {
+        self.branding = Some(branding);
+        self.rebuild_call_router();
+        self
+    }
+
+    /// Play held calls the MOH class of their queue or tenant
+    pub fn with_moh_classes(mut self, moh_classes: Arc<MohClassRegistry>) -> Self {
+        self.moh_classes = Some(moh_classes);
+        self.rebuild_call_router();
+        self
+    }
+
+    /// Apply trunk and route header rules to forwarded INVITEs
+    pub fn with_header_rules(mut self, header_rules: Arc<HeaderRulesEngine>) -> Self {
+        self.header_rules = Some(header_rules);
+        self.rebuild_call_router();
+        self
+    }
+
+    /// Debug the calls of pre-armed users
+    pub fn with_call_debug(mut self, call_debug: Arc<CallDebugRegistry>) -> Self {
+        self.call_debug = Some(call_debug);
+        self.rebuild_call_router();
+        self
+    }
+
+    /// Have callers of screening users record their name before ringing them
+    pub fn with_call_screening(mut self, screening: Arc<ScreeningService>) -> Self {
+        self.screening = Some(screening);
+        self.rebuild_call_router();
+        self
+    }
+
+    /// Record answered calls when the manager auto-records
+    pub fn with_call_recording(mut self, recording: Arc<CallRecordingManager>) -> Self {
+        self.recording = Some(recording);
+        self.rebuild_call_router();
+        self
+    }
+
+    /// Route ported numbers by the routing number or trunk their lookup
+    /// returns
+    pub fn with_lnp(mut self, lnp: Arc<LnpResolver>) -> Self {
+        self.lnp = Some(lnp);
+        self.rebuild_call_router();
+        self
+    }
+
+    /// Count the channels calls take on each trunk, keeping the ones
+    /// reserved per direction and failing outbound calls over
+    pub fn with_trunk_manager(mut self, trunks: Arc<TrunkManager>) -> Self {
+        self.trunks = Some(trunks);
+        self.rebuild_call_router();
+        self
+    }
+
+    /// Skip portability lookups and call debugging while `overload` is
+    /// shedding
+    pub fn with_overload_monitor(mut self, overload: Arc<OverloadMonitor>) -> Self {
+        self.overload = Some(overload);
+        self.rebuild_call_router();
+        self
+    }
+
+    /// Keep dialog CSeqs and the SSRCs of call media in `vault`
+    pub fn with_sequence_vault(mut self, vault: Arc<SequenceVault>) -> Self {
+        self.sequences = Some(vault);
+        self.rebuild_call_router();
+        self
+    }
+
+    /// Estimate the bandwidth of legs that offer rtcp-mux and goog-remb,
+    /// sending them REMB feedback
+    pub fn with_bandwidth_monitor(mut self, monitor: Arc<BandwidthMonitor>) -> Self {
+        self.bandwidth = Some(monitor);
+        self
+    }
+
+    /// Media port pool of new calls
+    pub fn port_allocator(&self) -> Arc<RtpPortAllocator> {
+        self.port_allocator.clone()
+    }
+
+    /// Simulator running this handler's routing components in the order
+    /// INVITEs go through them: those answering the call here, then the
+    /// registrar, then number portability and header rules of forwarded
+    /// INVITEs
+    pub fn routing_simulator(&self) -> RoutingSimulator {
+        let mut simulator = RoutingSimulator::new();
+        if let Some(feature_codes) = &self.feature_codes {
+            simulator = simulator.with_step(feature_codes.clone());
+        }
+        if let Some(speed_dials) = &self.speed_dials {
+            simulator = simulator.with_step(speed_dials.clone());
+        }
+        if let Some(feature_codes) = &self.feature_codes {
+            simulator = simulator.with_step(Arc::new(UnknownFeatureCode(feature_codes.clone())));
+        }
+        if let Some(maintenance) = &self.maintenance {
+            simulator = simulator.with_step(maintenance.clone());
+        }
+        if let Some(services) = &self.internal_services {
+            simulator = simulator.with_step(services.clone());
+        }
+        if let Some(announcements) = &self.announcements {
+            simulator = simulator.with_step(announcements.clone());
+        }
+        if let Some(auto_attendants) = &self.auto_attendants {
+            simulator = simulator.with_step(auto_attendants.clone());
+        }
+        if let Some(screening) = &self.screening {
+            simulator = simulator.with_step(screening.clone());
+        }
+        simulator = simulator.with_step(self.registrar.clone());
+        if let Some(lnp) = &self.lnp {
+            simulator = simulator.with_step(lnp.clone());
+        }
+        if let Some(header_rules) = &self.header_rules {
+            simulator = simulator.with_step(header_rules.clone());
+        }
+        simulator
+    }
+
+    /// Linter over this handler's routing components, in the order
+    /// INVITEs go through them
+    pub fn routing_linter(&self) -> RoutingLinter {
+        let mut linter = RoutingLinter::new();
+        if let Some(feature_codes) = &self.feature_codes {
+            linter = linter.with_source(feature_codes.clone());
+        }
+        if let Some(speed_dials) = &self.speed_dials {
+            linter = linter.with_source(speed_dials.clone());
+        }
+        if let Some(announcements) = &self.announcements {
+            linter = linter.with_source(announcements.clone());
+        }
+        if let Some(auto_attendants) = &self.auto_attendants {
+            linter = linter.with_source(auto_attendants.clone());
+        }
+        if let Some(header_rules) = &self.header_rules {
+            linter = linter.with_source(header_rules.clone());
+        }
+        linter
+    }
+
+    /// Send forwarded INVITEs to the NAPTR/SRV targets of their destination
+    pub fn with_sip_resolver(mut self, resolver: Arc<SipResolver>) -> Self {
+        self.sip_resolver = Some(resolver);
+        self.rebuild_call_router();
+        self
+    }
+
+    /// Time the setup of calls and check it against latency SLOs
+    pub fn with_setup_latency(mut self, monitor: Arc<SetupLatencyMonitor>) -> Self {
+        self.setup_latency = Some(monitor);
+        self.rebuild_call_router();
+        self
+    }
+
+    /// Who may take over whose calls with INVITE/Replaces
+    pub fn with_takeover_policy(mut self, takeover_policy: TakeoverPolicy) -> Self {
+        self.takeover_policy = takeover_policy;
+        self.rebuild_call_router();
+        self
+    }
+
+    /// Ringback policy and tone plans of forwarded calls
+    pub fn with_ringback(mut self, ringback: RingbackConfig) -> Self {
+        self.ringback = ringback;
+        self.rebuild_call_router();
+        self
+    }
+
+    /// Answer calls to the built-in test services' extensions
+    pub fn with_internal_services(mut self, internal_services: Arc<InternalServiceHandler>) -> Self {
+        self.internal_services = Some(internal_services);
+        self
+    }
+
+    /// Refuse new calls involving tenants and trunks in maintenance
+    pub fn with_maintenance(mut self, maintenance: Arc<MaintenanceRegistry>) -> Self {
+        self.maintenance = Some(maintenance);
+        self
+    }
+
+    /// Route calls locally while the upstream is unreachable
+    pub fn with_survivability(mut self, survivability: Arc<SurvivabilityManager>) -> Self {
+        self.survivability = Some(survivability);
+        self
+    }
+
+    /// Answer calls to announcement-only numbers with their message
+    pub fn with_announcements(mut self, announcements: Arc<AnnouncementService>) -> Self {
+        self.announcements = Some(announcements);
+        self
+    }
+
+    /// Answer calls to auto-attendant numbers with their menu; extensions
+    /// and names are looked up in `directory`, digits read from `dtmf`
+    pub fn with_auto_attendants(
+        mut self,
+        auto_attendants: Arc<AutoAttendantService>,
+        directory: Arc<dyn IvrDirectory>,
+        dtmf: Arc<DtmfDispatcher>,
+    ) -> Self {
+        self.auto_attendants = Some(auto_attendants);
+        self.ivr = Some(Arc::new(IvrFlowEngine::new().with_directory(directory)));
+        self.dtmf = Some(dtmf);
+        self
+    }
+
+    /// Transfer callers to the voicemail or forward destination following
+    /// an announcement (without one, they are hung up on)
+    pub fn with_forwarder(mut self, forwarder: Arc<dyn InviteForwarder>) -> Self {
+        self.forwarder = Some(forwarder);
+        self
+    }
+
+    /// Send the BYE or CANCEL ending a device's dialog when another device
+    /// takes over its call (otherwise it is only dropped from the call)
+    pub fn with_takeover_signaling(mut self, leg_releaser: Arc<dyn LegReleaser>) -> Self {
+        self.leg_releaser = Some(leg_releaser);
+        self
+    }
+
+    /// Replace call_router with one carrying the configured repositories
+    fn rebuild_call_router(&mut self) {
+        let mut router = CallRouter::new(self.registrar.clone())
+            .with_redirect_policy(self.redirect_policy.clone())
+            .with_transfer_policy(self.transfer_policy.clone())
+            .with_takeover_policy(self.takeover_policy.clone())
+            .with_ringback(self.ringback.clone());
+        if let Some(cdr_repository) = &self.cdr_repository {
+            router = router.with_cdr_repository(cdr_repository.clone());
+        }
+        if let Some(fraud_detector) = &self.fraud_detector {
+            router = router.with_fraud_detector(fraud_detector.clone());
+        }
+        if let Some(call_events) = &self.call_events {
+            router = router.with_call_events(call_events.clone());
+        }
+        if let Some(hops) = &self.hops {
+            router = router.with_hop_tracker(hops.clone());
+        }
+        if let Some(branding) = &self.branding {
+            router = router.with_branding(branding.clone());
+        }
+        if let Some(moh_classes) = &self.moh_classes {
+            router = router.with_moh_classes(moh_classes.clone());
+        }
+        if let Some(header_rules) = &self.header_rules {
+            router = router.with_header_rules(header_rules.clone());
+        }
+        if let Some(call_debug) = &self.call_debug {
+            router = router.with_call_debug(call_debug.clone());
+        }
+        if let Some(screening) = &self.screening {
+            router = router.with_call_screening(screening.clone());
+        }
+        if let Some(recording) = &self.recording {
+            router = router.with_call_recording(recording.clone());
+        }
+        if let Some(lnp) = &self.lnp {
+            router = router.with_lnp(lnp.clone());
+        }
+        if let Some(trunks) = &self.trunks {
+            router = router.with_trunk_manager(trunks.clone());
+        }
+        if let Some(overload) = &self.overload {
+            router = router.with_overload_monitor(overload.clone());
+        }
+        if let Some(sequences) = &self.sequences {
+            router = router.with_sequence_vault(sequences.clone());
+        }
+        if let Some(resolver) = &self.sip_resolver {
+            router = router.with_sip_resolver(resolver.clone());
+        }
+        if let Some(monitor) = &self.setup_latency {
+            router = router.with_setup_latency(monitor.clone());
+        }
+        self.call_router = Arc::new(router);
+    }
+
+    /// Realm (host part) of the caller URI, used to pick tenant rules
+    fn caller_realm(from_uri: &str) -> Option<&str> {
+        from_uri
+            .split_once('@')
+            .and_then(|(_, host)| host.split([':', ';', '>']).next())
+    }
+
+    /// Evaluate fraud rules for a call from `from_uri` to `to_uri`
+    fn check_fraud(&self, call_id: &str, from_uri: &str, to_uri: &str) -> FraudDecision {
+        let fraud = match &self.fraud_detector {
+            Some(fraud) => fraud,
+            None => return FraudDecision::Allow,
+        };
+
+        let username = CallRouter::extract_username(from_uri);
+        fraud.evaluate(OutboundCall {
+            realm: Self::caller_realm(from_uri),
+            username: &username,
+            call_id,
+            destination: to_uri,
+        })
+    }
+
+    /// Play the fraud confirmation prompt into a call
+    fn play_fraud_confirmation(&self, call_id: &str, from_uri: &str) {
+        let (fraud, announcer) = match (&self.fraud_detector, &self.call_announcer) {
+            (Some(fraud), Some(announcer)) => (fraud, announcer),
+            _ => {
+                warn!("No call announcer configured, cannot confirm call {}", call_id);
+                return;
+            }
+        };
+
+        let realm = Self::caller_realm(from_uri);
+        let prompt = fraud.rules_for(realm).confirmation_prompt;
+        let mut request = AnnouncementRequest::new(call_id.to_string(), AnnouncementType::Custom)
+            .add_audio(&prompt)
+            .immediate();
+        if let Some(branding) = &self.branding {
+            request = branding.brand_announcement(realm, request);
+        }
+        if let Err(e) = announcer.play_announcement(request) {
+            warn!("Failed to play fraud confirmation for call {}: {}", call_id, e);
+        }
+    }
+
+    /// Run PBX features for dialed feature codes, and answer unknown star
+    /// codes with the registry's announcement
+    pub fn with_feature_codes(mut self, feature_codes: Arc<FeatureCodeRegistry>) -> Self {
+        self.feature_codes = Some(feature_codes);
+        self
+    }
+
+    /// User and host parts of a request URI
+    fn dialed_user(to_uri: &str) -> Option<(String, String)> {
+        to_uri
+            .trim_start_matches("sips:")
+            .trim_start_matches("sip:")
+            .split_once('@')
+            .map(|(user, host)| (user.to_string(), host.to_string()))
+    }
+
+    /// Run the feature a dialed feature code invokes
+    ///
+    /// Returns the feature's outcome and the code as dialed, or `None` when
+    /// the request does not dial a feature enabled for the caller's tenant.
+    async fn invoke_feature_code(
+        &self,
+        call_id: &str,
+        from_uri: &str,
+        to_uri: &str,
+    ) -> Option<(FeatureOutcome, String)> {
+        let registry = self.feature_codes.as_ref()?;
+        let (dialed, domain) = Self::dialed_user(to_uri)?;
+        let realm = Self::caller_realm(from_uri);
+        let feature = registry.lookup(realm, &dialed)?;
+
+        let caller = CallRouter::extract_username(from_uri);
+        info!("Feature code {} ({}) dialed by {}", dialed, feature.code.feature, caller);
+        let outcome = feature
+            .invoke(&FeatureCall {
+                call_id: call_id.to_string(),
+                caller,
+                realm: realm.map(str::to_string),
+                domain,
+                dialed: dialed.clone(),
+                argument: feature.argument.clone(),
+            })
+            .await;
+        Some((outcome, dialed))
+    }
+
+    /// Outcome for a dialed star code that is neither a feature nor a
+    /// speed dial
+    fn unknown_feature_code(&self, to_uri: &str) -> Option<(FeatureOutcome, String)> {
+        let registry = self.feature_codes.as_ref()?;
+        let (dialed, _) = Self::dialed_user(to_uri)?;
+        if !is_feature_code_pattern(&dialed) {
+            return None;
+        }
+        info!("Unknown feature code {} dialed", dialed);
+        Some((registry.unknown_code(), dialed))
+    }
+
+    /// Resolve speed dial codes before routing
+    pub fn with_speed_dials(mut self, speed_dials: Arc<SpeedDialService>) -> Self {
+        self.speed_dials = Some(speed_dials);
         self
     }
 
+    /// Rewrite a dialed speed dial code to its target
+    ///
+    /// Returns the resolved callee URI and the code as dialed, or the
+    /// original URI when the request does not dial a known code.
+    async fn resolve_speed_dial(&self, from_uri: &str, to_uri: &str) -> (String, Option<String>) {
+        let rewritten = match &self.speed_dials {
+            Some(speed_dials) => speed_dials.rewrite(from_uri, to_uri).await,
+            None => None,
+        };
+        match rewritten {
+            Some((speed_dial, target)) => {
+                let caller = CallRouter::extract_username(from_uri);
+                info!("Speed dial {} from {} resolved to {}", speed_dial.code, caller, target);
+                (target, Some(speed_dial.code))
+            }
+            None => (to_uri.to_string(), None),
+        }
+    }
+
     /// Get call router reference
     pub fn call_router(&self) -> Arc<CallRouter> {
         self.call_router.clone()
     }
 
-    /// Allocate RTP port pair (RTP + RTCP)
-    async fn allocate_rtp_port(&self) -> u16 {
-        let mut port = self.next_rtp_port.write().await;
-        let allocated = *port;
-        *port = port.wrapping_add(2); // Allocate RTP and RTCP
-        if *port < 10000 {
-            *port = 10000; // Wrap around
+    /// Fail a call during setup: record the rejection, close anything
+    /// allocated for it and answer the INVITE with `status_code`
+    async fn abort_call(
+        &self,
+        call_id: &str,
+        reason: &str,
+        status_code: u16,
+        request: &SipRequest,
+    ) -> Result<SipResponse, SipError> {
+        if let Err(e) = self.call_router.reject_call(call_id, reason).await {
+            debug!("Call {} not rejected in router: {}", call_id, e);
+        }
+        self.call_router.discard_call(call_id).await;
+
+        let session = self.active_calls.write().await.remove(call_id);
+        if let Some(bridge) = session.and_then(|s| s.media_bridge) {
+            bridge.close().await;
         }
-        allocated
+
+        warn!("Call {} aborted with {}: {}", call_id, status_code, reason);
+        ResponseBuilder::new(status_code).build_for_request(request)
     }
 
     async fn handle_invite(&self, request: &SipRequest) -> Result<SipResponse, SipError> {
         info!("Handling INVITE request");
+        let mut setup_timeline = SetupTimeline::starting_at(Instant::now());
 
         // Check authentication if enabled
+        let mut authenticated_user = None;
         if let Some(auth) = &self.auth {
             // Check if Authorization header is present
             let has_auth = request.headers().iter().any(|h| {
                 matches!(h, Header::Authorization(_) | Header::ProxyAuthorization(_))
             });
 
-            if !has_auth {
-                // Send 407 Proxy Authentication Required with challenge
-                warn!("INVITE without authentication - sending challenge");
-                let challenge = auth.create_challenge().await;
+            if !has_auth {
+                // Send 407 Proxy Authentication Required with challenge
+                warn!("INVITE without authentication - sending challenge");
+                let challenge = auth.create_challenge().await;
+
+                return ResponseBuilder::new(407)
+                    .header(Header::Other(
+                        "Proxy-Authenticate".to_string(),
+                        challenge.to_header_value(),
+                    ))
+                    .build_for_request(request);
+            }
+
+            // Verify authentication
+            match auth.verify_request(request, "INVITE").await {
+                Ok(username) => {
+                    info!("INVITE authenticated for user: {}", username);
+                    setup_timeline.mark(SetupMilestone::Authenticated, Instant::now());
+                    authenticated_user = Some(username);
+                }
+                Err(e) => {
+                    warn!("Authentication failed: {:?}", e);
+                    // Send 407 with new challenge
+                    let challenge = auth.create_challenge().await;
+
+                    return ResponseBuilder::new(407)
+                        .header(Header::Other(
+                            "Proxy-Authenticate".to_string(),
+                            challenge.to_header_value(),
+                        ))
+                        .build_for_request(request);
+                }
+            }
+        }
+
+        // Extract call information
+        let call_id = request.call_id().unwrap_or_else(|| "unknown".to_string());
+        let from_uri = self.extract_from_uri(request);
+        let to_uri = self.extract_to_uri(request);
+
+        debug!("Call: {} -> {}", from_uri, to_uri);
+
+        // Check if this is a re-INVITE (call already exists), possibly from
+        // a device that took the call over
+        let existing_call_id = self.call_router.canonical_call_id(&call_id).await;
+        if let Some(_call_state) = self.call_router.get_call_state(&existing_call_id).await {
+            info!("Detected re-INVITE for existing call {}", existing_call_id);
+            return self.handle_reinvite(request, &existing_call_id).await;
+        }
+
+        // Another device taking over a call (outside attended transfer)
+        if let Some(replaces) = header_value(request, "Replaces") {
+            let requester_user = authenticated_user
+                .unwrap_or_else(|| CallRouter::extract_username(&from_uri));
+            return self.handle_replaces(request, &replaces, requester_user).await;
+        }
+
+        // Feature codes, then speed dials, before any routing decision
+        let (to_uri, dialed, feature) =
+            match self.invoke_feature_code(&call_id, &from_uri, &to_uri).await {
+                Some((FeatureOutcome::Route(target), code)) => (target, Some(code), None),
+                Some(feature) => (to_uri, None, Some(feature)),
+                None => {
+                    let (to_uri, dialed) = self.resolve_speed_dial(&from_uri, &to_uri).await;
+                    let unknown = match dialed {
+                        Some(_) => None,
+                        None => self.unknown_feature_code(&to_uri),
+                    };
+                    (to_uri, dialed, unknown)
+                }
+            };
+        if let Some((outcome, code)) = feature {
+            return match outcome {
+                FeatureOutcome::Announce(prompt) => {
+                    let announcement_secs = self
+                        .feature_codes
+                        .as_ref()
+                        .map(|registry| registry.announcement_secs())
+                        .unwrap_or_default();
+                    self.handle_announcement(
+                        request,
+                        from_uri,
+                        to_uri,
+                        Some(code),
+                        &[prompt.as_str()],
+                        AfterPrompts::FollowUp {
+                            after_secs: announcement_secs,
+                            target: None,
+                        },
+                    )
+                    .await
+                }
+                FeatureOutcome::Respond(status) => {
+                    ResponseBuilder::new(status).build_for_request(request)
+                }
+                // Routed calls continue above
+                FeatureOutcome::Route(_) => ResponseBuilder::new(500).build_for_request(request),
+            };
+        }
+
+        // Tenants and trunks in maintenance take no new calls; internal
+        // callers hear the announcement when one is set
+        if let Some(maintenance) = &self.maintenance {
+            if let Some(state) = maintenance.check_call(&from_uri, &to_uri, peer_ip(request)) {
+                info!("Call {} refused: {} in maintenance", call_id, state.scope);
+                if let (None, Some(prompt)) = (self.trunk_of(request), &state.announcement) {
+                    return self
+                        .handle_announcement(
+                            request,
+                            from_uri,
+                            to_uri,
+                            dialed,
+                            &[prompt.as_str()],
+                            AfterPrompts::FollowUp {
+                                after_secs: MAINTENANCE_ANNOUNCEMENT_SECS,
+                                target: None,
+                            },
+                        )
+                        .await;
+                }
+                return ResponseBuilder::new(503)
+                    .header(Header::Other("Retry-After".to_string(), "300".to_string()))
+                    .build_for_request(request);
+            }
+        }
+
+        // Test services answer themselves, without a registrar lookup
+        if let Some(services) = &self.internal_services {
+            if let Some(service) = services.service_for(&to_uri) {
+                return self
+                    .handle_internal_service(request, services, service, from_uri, to_uri, dialed)
+                    .await;
+            }
+        }
+
+        // So do announcement-only numbers
+        if let Some(announcements) = &self.announcements {
+            if let Some(planned) = announcements.resolve(&to_uri).await {
+                return self
+                    .handle_info_line(request, planned, from_uri, to_uri, dialed)
+                    .await;
+            }
+        }
+
+        // And auto-attendants
+        if let Some(auto_attendants) = &self.auto_attendants {
+            if let Some(planned) = auto_attendants.resolve(&to_uri).await {
+                let call_id = request.call_id().unwrap_or_else(|| "unknown".to_string());
+                info!("Call {} answered by auto-attendant {}", call_id, planned.attendant_id);
+                let greeting = planned.greeting.clone();
+                return self
+                    .handle_announcement(
+                        request,
+                        from_uri,
+                        to_uri,
+                        dialed,
+                        &[greeting.as_str()],
+                        AfterPrompts::Attendant(planned),
+                    )
+                    .await;
+            }
+        }
+
+        // Class of service of local callers on the resolved destination
+        if let (Some(cos), None) = (&self.class_of_service, self.trunk_of(request)) {
+            let username = CallRouter::extract_username(&from_uri);
+            let realm = Self::caller_realm(&from_uri);
+            let check = ClassCheck {
+                realm,
+                username: &username,
+                destination: &to_uri,
+            };
+            let decision = cos.check(check, Utc::now());
+            if !decision.allowed {
+                let reason = format!(
+                    "Class of service {} does not allow {} calls",
+                    decision.class,
+                    decision.category.as_str()
+                );
+                info!("Call {} from {} to {} refused: {}", call_id, from_uri, to_uri, reason);
+                if cos.has_authorization_codes(realm) {
+                    let prompt = cos.code_prompt().to_string();
+                    return self
+                        .handle_announcement(
+                            request,
+                            from_uri,
+                            to_uri,
+                            dialed,
+                            &[prompt.as_str()],
+                            AfterPrompts::AuthorizationCode(decision.category),
+                        )
+                        .await;
+                }
+                if let Some(prompt) = cos.announcement().map(str::to_string) {
+                    let response = self
+                        .handle_announcement(
+                            request,
+                            from_uri,
+                            to_uri,
+                            dialed,
+                            &[prompt.as_str()],
+                            AfterPrompts::FollowUp {
+                                after_secs: COS_ANNOUNCEMENT_SECS,
+                                target: None,
+                            },
+                        )
+                        .await?;
+                    if response.status_code() == 200 {
+                        self.call_router.mark_blocked_cos(&call_id, &reason).await;
+                    }
+                    return Ok(response);
+                }
+                self.call_router
+                    .record_blocked_cos(&call_id, &from_uri, &to_uri, dialed, &reason)
+                    .await;
+                return ResponseBuilder::new(403).build_for_request(request);
+            }
+        }
+
+        // Toll fraud rules on the resolved destination
+        let confirm_call = match self.check_fraud(&call_id, &from_uri, &to_uri) {
+            FraudDecision::Block(reason) => {
+                warn!("Outbound call {} from {} blocked: {}", call_id, from_uri, reason);
+                return ResponseBuilder::new(403)
+                    .build_for_request(request);
+            }
+            FraudDecision::Confirm(_) => true,
+            FraudDecision::Warn(_) | FraudDecision::Allow => false,
+        };
+
+        // Check if callee is registered
+        let callee_available = self.call_router.is_callee_available(&to_uri).await;
+
+        // Without the upstream, extensions still reach each other and
+        // emergency and local numbers go out the local gateway; internal
+        // callers of other destinations hear the announcement when one is set
+        let route = match &self.survivability {
+            Some(survivability) => survivability.route(&to_uri, callee_available),
+            None => SurvivableRoute::Normal,
+        };
+        let (to_uri, gateway) = match route.clone() {
+            SurvivableRoute::Normal | SurvivableRoute::Local => (to_uri, None),
+            SurvivableRoute::Gateway { trunk, target_uri, address } => {
+                info!("Call {} to {} out local gateway {}", call_id, to_uri, trunk);
+                (target_uri, Some((trunk, address)))
+            }
+            SurvivableRoute::Restricted { announcement } => {
+                info!("Call {} to {} refused: upstream unreachable", call_id, to_uri);
+                if let (None, Some(prompt)) = (self.trunk_of(request), &announcement) {
+                    return self
+                        .handle_announcement(
+                            request,
+                            from_uri,
+                            to_uri,
+                            dialed,
+                            &[prompt.as_str()],
+                            AfterPrompts::FollowUp {
+                                after_secs: SURVIVABILITY_ANNOUNCEMENT_SECS,
+                                target: None,
+                            },
+                        )
+                        .await;
+                }
+                return ResponseBuilder::new(503)
+                    .header(Header::Other("Retry-After".to_string(), "60".to_string()))
+                    .build_for_request(request);
+            }
+        };
+
+        if !callee_available && gateway.is_none() {
+            warn!("Callee {} not found or not registered", to_uri);
+            return ResponseBuilder::new(404)
+                .build_for_request(request);
+        }
+
+        // Create call in router
+        setup_timeline.mark(SetupMilestone::Routed, Instant::now());
+        if let Err(e) = self.call_router.create_call_with_setup(
+            call_id.clone(),
+            from_uri.clone(),
+            to_uri.clone(),
+            dialed,
+            Some(setup_timeline),
+        ).await {
+            warn!("Failed to create call: {}", e);
+            return ResponseBuilder::new(500)
+                .build_for_request(request);
+        }
+        if let Some(trunk) = self.trunk_of(request) {
+            if let Err(e) = self.call_router.seize_inbound_trunk(&call_id, trunk).await {
+                warn!("Rejecting call {}: {}", call_id, e);
+                self.call_router.discard_call(&call_id).await;
+                return ResponseBuilder::new(503)
+                    .header(Header::Other("Retry-After".to_string(), "30".to_string()))
+                    .build_for_request(request);
+            }
+        }
+        if let Some((trunk, address)) = &gateway {
+            self.call_router.set_trunk(&call_id, trunk).await;
+            self.call_router.set_callee_contact(&call_id, *address).await;
+        }
+        if let (Some(survivability), false) =
+            (&self.survivability, route == SurvivableRoute::Normal)
+        {
+            survivability.defer(DeferredItem::Cdr { call_id: call_id.clone() });
+        }
+
+        // Dialog identity, so the call can be named in a Replaces header
+        let local_tag = Uuid::new_v4().simple().to_string();
+        self.call_router
+            .set_dialog_tags(&call_id, request.from_tag(), local_tag.clone())
+            .await;
+        if let Some(contact) = header_value(request, "Contact").and_then(|c| uri_socket_addr(&c)) {
+            self.call_router.set_caller_contact(&call_id, contact).await;
+        }
+
+        if confirm_call {
+            self.play_fraud_confirmation(&call_id, &from_uri);
+        }
+
+        // If not in auto-answer mode, send 180 Ringing; as a transaction
+        // user, the 200 OK follows once the callee answers
+        if !self.auto_answer {
+            info!("Call {} ringing (forward mode)", call_id);
+            return self.call_router.send_ringing(&call_id, request).await;
+        }
+
+        let media = match self.negotiate_media(request).await {
+            Ok(media) => media,
+            Err((status_code, reason)) => {
+                return self.abort_call(&call_id, reason, status_code, request).await;
+            }
+        };
+        let packet_format = media.format;
+
+        // For auto-answer mode, create a simple bridge (in real implementation, you'd connect two different streams)
+        let media_stream = media.stream.clone();
+        let media_bridge = Arc::new(
+            MediaBridge::new(media_stream.clone(), media_stream)
+                .with_packet_formats(packet_format, packet_format),
+        );
+
+        // Create call session
+        let session = CallSession {
+            call_id: call_id.clone(),
+            from_uri: from_uri.clone(),
+            to_uri: to_uri.clone(),
+            state: CallSessionState::Inviting,
+            media_bridge: Some(media_bridge.clone()),
+        };
+
+        {
+            let mut calls = self.active_calls.write().await;
+            calls.insert(call_id.clone(), session);
+        }
+        self.call_router.set_media_bridge(&call_id, media_bridge.clone()).await;
+
+        // Auto-answer mode
+        info!("Auto-answering call {}", call_id);
+
+        // A screened caller is answered to record their name, but the call
+        // is not bridged (nor recorded as answered) until the user accepts
+        let screening_prompt = match self.call_router.screen_call(&call_id).await {
+            Ok(prompt) => prompt,
+            Err(e) => {
+                warn!("Failed to screen call {}: {}", call_id, e);
+                None
+            }
+        };
+        if let Some(prompt) = screening_prompt {
+            if let Some(announcer) = &self.call_announcer {
+                if let Err(e) = announcer.play_announcement(prompt) {
+                    warn!("Failed to prompt screened caller of call {}: {}", call_id, e);
+                }
+            }
+        } else if let Err(e) = self.call_router.answer_call(&call_id).await {
+            // Answer call in router; fails when a CANCEL won the race
+            warn!("Failed to answer call in router: {}", e);
+            return self.abort_call(&call_id, "cancelled during setup", 487, request).await;
+        }
+
+        // Update legacy call state
+        {
+            let mut calls = self.active_calls.write().await;
+            if let Some(call) = calls.get_mut(&call_id) {
+                call.state = CallSessionState::Answered;
+            }
+        }
+
+        self.answer_offer(request, &call_id, &local_tag, &media).await
+    }
+
+    /// 200 OK answering the INVITE's offer with our media
+    ///
+    /// The dialog keeps the SDP origin stable for any later re-INVITE
+    /// answers. The call is aborted if the response cannot be built.
+    async fn answer_offer(
+        &self,
+        request: &SipRequest,
+        call_id: &str,
+        local_tag: &str,
+        media: &NegotiatedMedia,
+    ) -> Result<SipResponse, SipError> {
+        // Create SDP answer with the negotiated codecs
+        let sdp = self.negotiated_sdp(request, media);
+        let offer_body = String::from_utf8_lossy(request.body()).to_string();
+        let cseq = request.cseq().unwrap_or(1);
+        let sdp_body = self
+            .call_router
+            .dialog_manager()
+            .with_dialog(call_id, false, |d| d.answer_offer(cseq, &offer_body, sdp))
+            .await;
+
+        // Build 200 OK response with SDP
+        let response = match ResponseBuilder::ok()
+            .to_tag(local_tag)
+            .body(sdp_body.into_bytes())
+            .build_for_request(request)
+        {
+            Ok(response) => response,
+            Err(e) => {
+                let _ = self.abort_call(call_id, "failed to build answer", 500, request).await;
+                return Err(e);
+            }
+        };
+
+        info!("Sent 200 OK for call {}", call_id);
+
+        Ok(response)
+    }
+
+    /// Final response to the INVITE of a ringing call: 200 OK with our
+    /// media once the callee answered, 487 when the call ended first (e.g.
+    /// on CANCEL)
+    async fn await_answer(&self, request: &SipRequest) -> Result<SipResponse, SipError> {
+        let call_id = request.call_id().unwrap_or_else(|| "unknown".to_string());
+        let Some(mut state) = self.call_router.watch_call_state(&call_id).await else {
+            return ResponseBuilder::new(487).build_for_request(request);
+        };
+        loop {
+            let current = state.borrow_and_update().clone();
+            if current == CallState::Established {
+                break;
+            }
+            if !current.is_active() || state.changed().await.is_err() {
+                info!("Call {} ended before it was answered", call_id);
+                return ResponseBuilder::new(487).build_for_request(request);
+            }
+        }
+
+        let local_tag = match self.call_router.dialog_tags(&call_id).await {
+            Some((_, Some(local_tag))) => local_tag,
+            _ => Uuid::new_v4().simple().to_string(),
+        };
+        let media = match self.negotiate_media(request).await {
+            Ok(media) => media,
+            Err((status_code, reason)) => {
+                return self.abort_call(&call_id, reason, status_code, request).await;
+            }
+        };
+        let offer = SdpSession::parse(&String::from_utf8_lossy(request.body()));
+        if let Some((remote, rtcp)) =
+            offer.and_then(|o| Some((o.audio_rtp_address()?, o.audio_rtcp_address()?)))
+        {
+            media.stream.set_remote(remote, rtcp).await;
+            media.stream.set_latching(LatchConfig::for_leg(remote.ip(), signaling_ip(request)));
+        }
+        self.call_router
+            .set_leg_stream(&call_id, &CallLeg::Caller, media.stream.clone())
+            .await;
+
+        self.active_calls.write().await.insert(
+            call_id.clone(),
+            CallSession {
+                call_id: call_id.clone(),
+                from_uri: self.extract_from_uri(request),
+                to_uri: self.extract_to_uri(request),
+                state: CallSessionState::Answered,
+                media_bridge: None,
+            },
+        );
+
+        self.answer_offer(request, &call_id, &local_tag, &media).await
+    }
+
+    /// Answer a call to a built-in test service
+    ///
+    /// The call is refused busy when the service has its maximum number of
+    /// calls. Its CDR is flagged as a test call; the service ends the call
+    /// when done or at its maximum duration.
+    async fn handle_internal_service(
+        &self,
+        request: &SipRequest,
+        services: &Arc<InternalServiceHandler>,
+        service: InternalService,
+        from_uri: String,
+        to_uri: String,
+        dialed: Option<String>,
+    ) -> Result<SipResponse, SipError> {
+        let call_id = request.call_id().unwrap_or_else(|| "unknown".to_string());
+        if let Err(e) = services.reserve(&call_id, service) {
+            warn!("Call {} to {} refused: {}", call_id, to_uri, e);
+            return ResponseBuilder::new(e.status_code()).build_for_request(request);
+        }
+
+        if let Err(e) = self
+            .call_router
+            .create_dialed_call(call_id.clone(), from_uri.clone(), to_uri.clone(), dialed)
+            .await
+        {
+            warn!("Failed to create test call: {}", e);
+            services.release(&call_id);
+            return ResponseBuilder::new(500).build_for_request(request);
+        }
+        self.call_router.mark_test_call(&call_id).await;
+
+        let local_tag = Uuid::new_v4().simple().to_string();
+        self.call_router
+            .set_dialog_tags(&call_id, request.from_tag(), local_tag.clone())
+            .await;
+        if let Some(contact) = header_value(request, "Contact").and_then(|c| uri_socket_addr(&c)) {
+            self.call_router.set_caller_contact(&call_id, contact).await;
+        }
+
+        let media = match self.negotiate_media(request).await {
+            Ok(media) => media,
+            Err((status_code, reason)) => {
+                services.release(&call_id);
+                return self.abort_call(&call_id, reason, status_code, request).await;
+            }
+        };
+        let offer = SdpSession::parse(&String::from_utf8_lossy(request.body()));
+        if let Some((remote, rtcp)) =
+            offer.and_then(|o| Some((o.audio_rtp_address()?, o.audio_rtcp_address()?)))
+        {
+            media.stream.set_remote(remote, rtcp).await;
+            media.stream.set_latching(LatchConfig::for_leg(remote.ip(), signaling_ip(request)));
+        }
+        // Closed with the call, which stops the service
+        self.call_router
+            .set_leg_stream(&call_id, &CallLeg::Caller, media.stream.clone())
+            .await;
+
+        if let Err(e) = self.call_router.answer_call(&call_id).await {
+            warn!("Failed to answer test call in router: {}", e);
+            services.release(&call_id);
+            return self.abort_call(&call_id, "cancelled during setup", 487, request).await;
+        }
+        if let Err(e) = services.start(
+            self.call_router.clone(),
+            &call_id,
+            media.stream.clone(),
+            media.format,
+        ) {
+            warn!("Failed to start test service for call {}: {}", call_id, e);
+            return self.abort_call(&call_id, "test service unavailable", 500, request).await;
+        }
+
+        self.active_calls.write().await.insert(
+            call_id.clone(),
+            CallSession {
+                call_id: call_id.clone(),
+                from_uri,
+                to_uri,
+                state: CallSessionState::Answered,
+                media_bridge: None,
+            },
+        );
 
-                return ResponseBuilder::new(407)
-                    .header(Header::Other(
-                        "Proxy-Authenticate".to_string(),
-                        challenge.to_header_value(),
-                    ))
-                    .build_for_request(request);
-            }
+        self.answer_offer(request, &call_id, &local_tag, &media).await
+    }
 
-            // Verify authentication
-            match auth.verify_request(request, "INVITE").await {
-                Ok(username) => {
-                    info!("INVITE authenticated for user: {}", username);
-                }
-                Err(e) => {
-                    warn!("Authentication failed: {:?}", e);
-                    // Send 407 with new challenge
-                    let challenge = auth.create_challenge().await;
+    /// Answer a call to an announcement-only number, play the message
+    /// planned for it and carry out the follow-up
+    async fn handle_info_line(
+        &self,
+        request: &SipRequest,
+        planned: PlannedAnnouncement,
+        from_uri: String,
+        to_uri: String,
+        dialed: Option<String>,
+    ) -> Result<SipResponse, SipError> {
+        let call_id = request.call_id().unwrap_or_else(|| "unknown".to_string());
+        info!(
+            "Call {} to announcement route {}: message {}",
+            call_id, planned.route_id, planned.variant
+        );
+        let prompts = vec![planned.audio_file.as_str(); planned.repeat as usize];
+        let response = self
+            .handle_announcement(
+                request,
+                from_uri,
+                to_uri,
+                dialed,
+                &prompts,
+                AfterPrompts::FollowUp {
+                    after_secs: planned.play_secs,
+                    target: planned.follow_up_uri.clone(),
+                },
+            )
+            .await?;
+        if response.status_code() == 200 {
+            self.call_router
+                .mark_announcement(&call_id, &planned.variant.to_string())
+                .await;
+        }
+        Ok(response)
+    }
 
-                    return ResponseBuilder::new(407)
-                        .header(Header::Other(
-                            "Proxy-Authenticate".to_string(),
-                            challenge.to_header_value(),
-                        ))
-                        .build_for_request(request);
+    /// Transfer an answered call to `target`, or hang it up (also when the
+    /// transfer fails)
+    async fn follow_up(
+        router: &CallRouter,
+        forwarder: Option<Arc<dyn InviteForwarder>>,
+        call_id: &str,
+        target: Option<String>,
+    ) {
+        if let Some(target) = target {
+            match forwarder {
+                Some(forwarder) => {
+                    let transferred = match router.blind_transfer(call_id, &target).await {
+                        Ok(()) => {
+                            router
+                                .execute_transfer(call_id, forwarder.as_ref(), &NoTransferor)
+                                .await
+                        }
+                        Err(e) => Err(e),
+                    };
+                    match transferred {
+                        Ok(outcome) => {
+                            debug!("Announcement call {} followed up: {:?}", call_id, outcome);
+                            return;
+                        }
+                        Err(e) => warn!(
+                            "Failed to transfer announcement call {} to {}: {}",
+                            call_id, target, e
+                        ),
+                    }
                 }
+                None => warn!(
+                    "No forwarder configured, hanging up announcement call {} instead of transferring to {}",
+                    call_id, target
+                ),
             }
         }
+        if let Err(e) = router.terminate_call(call_id).await {
+            debug!("Announcement call {} already ended: {}", call_id, e);
+        }
+    }
 
-        // Extract call information
+    /// Answer a call, play `prompts` and carry out `after`: hang up or
+    /// transfer the caller once the prompts are over, or run an
+    /// auto-attendant's menu
+    ///
+    /// Used for feature code results, maintenance announcements,
+    /// announcement-only numbers, auto-attendants and class of service
+    /// refusals.
+    async fn handle_announcement(
+        &self,
+        request: &SipRequest,
+        from_uri: String,
+        to_uri: String,
+        dialed: Option<String>,
+        prompts: &[&str],
+        after: AfterPrompts,
+    ) -> Result<SipResponse, SipError> {
         let call_id = request.call_id().unwrap_or_else(|| "unknown".to_string());
-        let from_uri = self.extract_from_uri(request);
-        let to_uri = self.extract_to_uri(request);
+        if let Err(e) = self
+            .call_router
+            .create_dialed_call(call_id.clone(), from_uri.clone(), to_uri.clone(), dialed)
+            .await
+        {
+            warn!("Failed to create announcement call: {}", e);
+            return ResponseBuilder::new(500).build_for_request(request);
+        }
 
-        debug!("Call: {} -> {}", from_uri, to_uri);
+        let local_tag = Uuid::new_v4().simple().to_string();
+        self.call_router
+            .set_dialog_tags(&call_id, request.from_tag(), local_tag.clone())
+            .await;
+        if let Some(contact) = header_value(request, "Contact").and_then(|c| uri_socket_addr(&c)) {
+            self.call_router.set_caller_contact(&call_id, contact).await;
+        }
 
-        // Check if this is a re-INVITE (call already exists)
-        if let Some(_call_state) = self.call_router.get_call_state(&call_id).await {
-            info!("Detected re-INVITE for existing call {}", call_id);
-            return self.handle_reinvite(request, &call_id).await;
+        let media = match self.negotiate_media(request).await {
+            Ok(media) => media,
+            Err((status_code, reason)) => {
+                return self.abort_call(&call_id, reason, status_code, request).await;
+            }
+        };
+        let offer = SdpSession::parse(&String::from_utf8_lossy(request.body()));
+        if let Some((remote, rtcp)) =
+            offer.and_then(|o| Some((o.audio_rtp_address()?, o.audio_rtcp_address()?)))
+        {
+            media.stream.set_remote(remote, rtcp).await;
+            media.stream.set_latching(LatchConfig::for_leg(remote.ip(), signaling_ip(request)));
         }
+        self.call_router
+            .set_leg_stream(&call_id, &CallLeg::Caller, media.stream.clone())
+            .await;
 
-        // Check if callee is registered
-        let callee_available = self.call_router.is_callee_available(&to_uri).await;
+        if let Err(e) = self.call_router.answer_call(&call_id).await {
+            warn!("Failed to answer announcement call in router: {}", e);
+            return self.abort_call(&call_id, "cancelled during setup", 487, request).await;
+        }
 
-        if !callee_available {
-            warn!("Callee {} not found or not registered", to_uri);
-            return ResponseBuilder::new(404)
-                .build_for_request(request);
+        let announcer = self.call_announcer.clone();
+        let branding = self.branding.clone();
+        let realm = Self::caller_realm(&from_uri).map(str::to_string);
+        let play = move |call_id: &str, prompts: &[&str]| match &announcer {
+            Some(announcer) => {
+                let mut announcement = prompts.iter().fold(
+                    AnnouncementRequest::new(call_id.to_string(), AnnouncementType::Custom),
+                    |announcement, prompt| announcement.add_audio(prompt),
+                );
+                announcement = announcement.immediate();
+                if let Some(branding) = &branding {
+                    announcement = branding.brand_announcement(realm.as_deref(), announcement);
+                }
+                if let Err(e) = announcer.play_announcement(announcement) {
+                    warn!("Failed to play {:?} to call {}: {}", prompts, call_id, e);
+                }
+            }
+            None => warn!(
+                "No call announcer configured, cannot play {:?} to call {}",
+                prompts, call_id
+            ),
+        };
+        play(&call_id, prompts);
+
+        let router = self.call_router.clone();
+        let forwarder = self.forwarder.clone();
+        let hangup_call_id = call_id.clone();
+        match after {
+            AfterPrompts::FollowUp { after_secs, target } => {
+                tokio::spawn(async move {
+                    tokio::time::sleep(std::time::Duration::from_secs(after_secs)).await;
+                    Self::follow_up(&router, forwarder, &hangup_call_id, target).await;
+                });
+            }
+            AfterPrompts::Attendant(planned) => match (&self.ivr, &self.dtmf) {
+                (Some(ivr), Some(dtmf)) => {
+                    let ivr = ivr.clone();
+                    let mut digits = dtmf.subscribe(&call_id);
+                    tokio::spawn(async move {
+                        let outcome = ivr
+                            .run(&hangup_call_id, &planned.flow, &mut digits, |prompts| {
+                                let prompts: Vec<&str> = prompts.iter().map(String::as_str).collect();
+                                play(&hangup_call_id, &prompts);
+                            })
+                            .await;
+                        debug!("Auto-attendant call {} finished: {:?}", hangup_call_id, outcome);
+                        match outcome {
+                            IvrOutcome::Transfer(target) => {
+                                Self::follow_up(&router, forwarder, &hangup_call_id, Some(target)).await
+                            }
+                            IvrOutcome::Hangup => {
+                                Self::follow_up(&router, forwarder, &hangup_call_id, None).await
+                            }
+                            IvrOutcome::Ended => {}
+                        }
+                    });
+                }
+                _ => {
+                    warn!("No IVR engine configured, hanging up auto-attendant call {}", call_id);
+                    tokio::spawn(async move {
+                        Self::follow_up(&router, forwarder, &hangup_call_id, None).await;
+                    });
+                }
+            },
+            AfterPrompts::AuthorizationCode(category) => match (&self.class_of_service, &self.dtmf) {
+                (Some(cos), Some(dtmf)) => {
+                    let cos = cos.clone();
+                    let mut digits = dtmf.subscribe(&call_id);
+                    let username = CallRouter::extract_username(&from_uri);
+                    let realm = Self::caller_realm(&from_uri).map(str::to_string);
+                    let target = to_uri.clone();
+                    tokio::spawn(async move {
+                        let code = collect_digits(&mut digits, MAX_CODE_DIGITS, cos.code_timeout()).await;
+                        let check = ClassCheck {
+                            realm: realm.as_deref(),
+                            username: &username,
+                            destination: &target,
+                        };
+                        let authorized = match code {
+                            Some(code) => cos
+                                .authorize_code(check, &hangup_call_id, category, &code, Utc::now())
+                                .is_ok(),
+                            None => {
+                                info!("No authorization code entered in call {}", hangup_call_id);
+                                false
+                            }
+                        };
+                        if authorized {
+                            Self::follow_up(&router, forwarder, &hangup_call_id, Some(target)).await;
+                            return;
+                        }
+                        let reason = format!("No valid authorization code for {} call", category.as_str());
+                        router.mark_blocked_cos(&hangup_call_id, &reason).await;
+                        if let Some(prompt) = cos.announcement() {
+                            play(&hangup_call_id, &[prompt]);
+                            tokio::time::sleep(std::time::Duration::from_secs(COS_ANNOUNCEMENT_SECS)).await;
+                        }
+                        Self::follow_up(&router, forwarder, &hangup_call_id, None).await;
+                    });
+                }
+                _ => {
+                    warn!("No DTMF dispatcher configured, hanging up call {} awaiting an authorization code", call_id);
+                    tokio::spawn(async move {
+                        Self::follow_up(&router, forwarder, &hangup_call_id, None).await;
+                    });
+                }
+            },
         }
 
-        // Create call in router
-        if let Err(e) = self.call_router.create_call(
+        self.active_calls.write().await.insert(
             call_id.clone(),
-            from_uri.clone(),
-            to_uri.clone(),
-        ).await {
-            warn!("Failed to create call: {}", e);
-            return ResponseBuilder::new(500)
-                .build_for_request(request);
-        }
+            CallSession {
+                call_id: call_id.clone(),
+                from_uri,
+                to_uri,
+                state: CallSessionState::Answered,
+                media_bridge: None,
+            },
+        );
+
+        self.answer_offer(request, &call_id, &local_tag, &media).await
+    }
 
-        // Send 100 Trying immediately
-        // Note: In a real implementation, we'd send this as a separate response
-        // For now, we'll just log it
-        debug!("Would send 100 Trying for call {}", call_id);
+    /// Our audio SDP listing the negotiated codecs (our defaults when
+    /// nothing was negotiated)
+    fn local_sdp(
+        &self,
+        ip: IpAddr,
+        port: u16,
+        payloads: &PayloadMap,
+        ptime_ms: Option<u32>,
+    ) -> SdpSession {
+        let mut sdp = SdpSession::create_audio_session(ip, port);
+        if let Some(audio) = sdp.media.first_mut() {
+            if payloads.primary().is_some() {
+                audio.set_payloads(payloads);
+            }
+            if let Some(ptime_ms) = ptime_ms {
+                audio.set_ptime(ptime_ms);
+            }
+            if self.audio_level_extension {
+                audio
+                    .attributes
+                    .push(ExtMap::new(1, AUDIO_LEVEL_URI).to_attribute());
+            }
+        }
+        sdp
+    }
 
-        // If not in auto-answer mode, send 180 Ringing and wait for actual answer
-        if !self.auto_answer {
-            info!("Call {} ringing (forward mode)", call_id);
-            // In a real implementation, forward INVITE to callee here
-            // and return 180 Ringing
-            return self.call_router.send_ringing(&call_id, request).await;
+    /// Our SDP for media negotiated from the request's offer, with the RTCP
+    /// port or rtcp-mux the stream uses
+    fn negotiated_sdp(&self, request: &SipRequest, media: &NegotiatedMedia) -> SdpSession {
+        let mut sdp = self.local_sdp(
+            self.advertised_media_ip(media.local_ip, request),
+            media.local_port,
+            &media.payloads,
+            Some(media.format.ptime_ms),
+        );
+        if let Some(audio) = sdp.media.first_mut() {
+            audio.set_rtcp(media.stream.local_rtcp_port(), media.stream.is_rtcp_muxed());
+            if let Some(codec) = media.payloads.primary().filter(|_| media.stream.estimates_bandwidth()) {
+                audio.set_remb(codec.payload_type);
+            }
         }
+        sdp
+    }
 
+    /// Header rules trunk the request came from
+    fn trunk_of(&self, request: &SipRequest) -> Option<&str> {
+        let header_rules = self.header_rules.as_ref()?;
+        header_rules.trunk_of(&peer_ip(request)?.to_string())
+    }
+
+    /// Allocate and start local media answering the INVITE's SDP offer
+    ///
+    /// Fails with the status code and reason to reject the INVITE with;
+    /// nothing stays allocated then.
+    async fn negotiate_media(
+        &self,
+        request: &SipRequest,
+    ) -> Result<NegotiatedMedia, (u16, &'static str)> {
         // Parse SDP offer from request body
         let sdp_offer = {
             let body = request.body();
@@ -213,98 +1777,225 @@ impl InviteHandler {
             }
         };
 
-        // Negotiate codecs if we have an SDP offer
-        let (chosen_codec, local_port) = if let Some(offer) = sdp_offer {
-            let offered_codecs = offer.audio_codecs();
-            info!("Offered codecs: {:?}", offered_codecs);
-
-            let negotiated = self.codec_negotiator.negotiate(&offered_codecs);
-            if negotiated.is_empty() {
-                warn!("No common codecs found");
-                return ResponseBuilder::new(488) // Not Acceptable Here
-                    .build_for_request(request);
-            }
-
-            let chosen = negotiated[0].clone();
-            info!("Chosen codec: {} (PT {})", chosen.name, chosen.payload_type);
+        // Answer and bind media in the caller's address family
+        let media_ip = self.media_ip(sdp_offer.as_ref());
+
+        // RTCP shares the RTP port only when the offer asks for it (RFC
+        // 5761); otherwise the stream gets a pair and answers with a=rtcp
+        let rtcp_mux = sdp_offer
+            .as_ref()
+            .and_then(|offer| offer.audio_media())
+            .is_some_and(|audio| audio.rtcp_mux());
+        // Browsers ask for REMB feedback; it is sent compound with the
+        // reports, which then go out once per estimate update
+        let remb = rtcp_mux
+            && sdp_offer
+                .as_ref()
+                .and_then(|offer| offer.audio_media())
+                .is_some_and(|audio| audio.remb());
 
-            // Allocate RTP port for this call
-            let port = self.allocate_rtp_port().await;
-            (Some(chosen), port)
+        // Create media streams (simplified - both legs using same local stream for auto-answer)
+        // In real implementation, you would create separate streams for caller and callee.
+        // The guard closes the stream and returns its ports on every early
+        // return below.
+        let allocated = if rtcp_mux {
+            MediaStream::allocate_muxed(self.port_allocator.clone(), unspecified_like(media_ip), 0, 8000)
+                .await
         } else {
-            // No SDP offer, use default
-            let port = self.allocate_rtp_port().await;
-            (None, port)
+            MediaStream::allocate(self.port_allocator.clone(), unspecified_like(media_ip), 0, 8000)
+                .await
         };
-
-        // Create media streams (simplified - both legs using same local stream for auto-answer)
-        // In real implementation, you would create separate streams for caller and callee
-        let media_stream = match MediaStream::new(
-            local_port,
-            chosen_codec.as_ref().map(|c| c.payload_type).unwrap_or(0),
-            8000,
-        ).await {
-            Ok(stream) => Arc::new(stream),
+        let media = match allocated {
+            Ok(stream) => {
+                // Never reuse the SSRC a call's media had before a restart
+                let stream = match (&self.sequences, request.call_id()) {
+                    (Some(vault), Some(call_id)) => {
+                        stream.with_rtp_session(vault.rtp_session(&call_id, 0, 8000))
+                    }
+                    _ => stream,
+                };
+                let stream = match (&self.bandwidth, request.call_id()) {
+                    (Some(monitor), Some(call_id)) if remb => stream
+                        .with_bandwidth_estimation(monitor.register(&call_id))
+                        .with_rtcp_interval(BANDWIDTH_UPDATE_INTERVAL),
+                    _ => stream,
+                };
+                MediaStreamGuard::new(Arc::new(stream))
+            }
+            Err(e) if matches!(
+                e.kind(),
+                std::io::ErrorKind::AddrNotAvailable | std::io::ErrorKind::AddrInUse
+            ) =>
+            {
+                warn!("No RTP port for the call: {}", e);
+                return Err((503, "RTP ports exhausted"));
+            }
             Err(e) => {
                 warn!("Failed to create media stream: {}", e);
-                return ResponseBuilder::new(500)
-                    .build_for_request(request);
+                return Err((500, "media unavailable"));
+            }
+        };
+        let local_port = match media.stream().local_rtp_port() {
+            Ok(port) => port,
+            Err(e) => {
+                warn!("Failed to read media port: {}", e);
+                return Err((500, "media unavailable"));
             }
         };
 
+        // Negotiate codecs if we have an SDP offer
+        let mut packet_format = PacketFormat::new(0, self.codec_negotiator.offer_ptime());
+        let mut payloads = PayloadMap::default();
+        let mut direction = StreamDirection::SendRecv;
+        if let Some(offer) = sdp_offer {
+            info!("Offered codecs: {:?}", offer.audio_codecs());
+
+            // Dynamic payload types are the offerer's: keep its numbering
+            payloads = offer.negotiate_audio(&self.codec_negotiator);
+            let Some(chosen) = payloads.primary().cloned() else {
+                warn!("No common codecs found");
+                // Not Acceptable Here
+                return Err((488, "no common codec"));
+            };
+            info!(
+                "Chosen codec: {} (PT {}), telephone-event PT {:?}",
+                chosen.name, chosen.payload_type, payloads.telephone_event
+            );
+            media.stream().set_payloads(payloads.clone()).await;
+            direction = offer.audio_direction().reversed();
+
+            let audio = offer.audio_media();
+            let ptime = self.codec_negotiator.accept_ptime(
+                audio.and_then(|m| m.ptime()),
+                audio.and_then(|m| m.max_ptime()),
+            );
+            info!("Packet time: {}ms", ptime);
+            packet_format = PacketFormat::new(chosen.payload_type, ptime);
+        }
+
         // Start media stream
-        if let Err(e) = media_stream.start().await {
+        if let Err(e) = media.stream().start().await {
             warn!("Failed to start media stream: {}", e);
+            return Err((500, "media unavailable"));
         }
 
-        // Set stream direction
-        media_stream.set_direction(StreamDirection::SendRecv).await;
-
-        // For auto-answer mode, create a simple bridge (in real implementation, you'd connect two different streams)
-        let media_bridge = Arc::new(MediaBridge::new(media_stream.clone(), media_stream.clone()));
-
-        // Create call session
-        let session = CallSession {
-            call_id: call_id.clone(),
-            from_uri: from_uri.clone(),
-            to_uri: to_uri.clone(),
-            state: CallSessionState::Inviting,
-            media_bridge: Some(media_bridge.clone()),
-        };
+        // Set stream direction, complementing a sendonly/recvonly offer
+        media.stream().set_direction(direction).await;
 
+        if let (Some(monitor), Some(codec)) =
+            (self.codec_negotiator.capacity_monitor(), payloads.primary())
         {
-            let mut calls = self.active_calls.write().await;
-            calls.insert(call_id.clone(), session);
+            monitor.call_started(
+                &request.call_id().unwrap_or_default(),
+                self.trunk_of(request),
+                codec,
+                payloads.opus.map(|opus| opus.bitrate),
+            );
         }
 
-        // Auto-answer mode
-        info!("Auto-answering call {}", call_id);
+        Ok(NegotiatedMedia {
+            stream: media.disarm(),
+            format: packet_format,
+            payloads,
+            local_ip: media_ip,
+            local_port,
+        })
+    }
 
-        // Answer call in router
-        if let Err(e) = self.call_router.answer_call(&call_id).await {
-            warn!("Failed to answer call in router: {}", e);
-        }
+    /// Hand the call named in a Replaces header to the INVITE's sender
+    ///
+    /// The sender's media takes the replaced leg's place in the bridge, so
+    /// the other party is not interrupted, and the replaced device is sent a
+    /// BYE (or a CANCEL when the call was still ringing, which makes this a
+    /// pickup).
+    async fn handle_replaces(
+        &self,
+        request: &SipRequest,
+        replaces: &str,
+        requester_user: String,
+    ) -> Result<SipResponse, SipError> {
+        let call_id = request.call_id().unwrap_or_else(|| "unknown".to_string());
+        let Some(replaces) = Replaces::parse(replaces) else {
+            warn!("INVITE {} with malformed Replaces: {}", call_id, replaces);
+            return ResponseBuilder::new(400).build_for_request(request);
+        };
+        info!(
+            "INVITE {} from {} replaces call {}",
+            call_id, requester_user, replaces.call_id
+        );
 
-        // Update legacy call state
+        let media = match self.negotiate_media(request).await {
+            Ok(media) => media,
+            Err((status_code, reason)) => {
+                warn!("Takeover INVITE {} rejected with {}: {}", call_id, status_code, reason);
+                return ResponseBuilder::new(status_code).build_for_request(request);
+            }
+        };
+        let takeover = TakeoverRequest {
+            replaces,
+            call_id: call_id.clone(),
+            requester_uri: self.extract_from_uri(request),
+            requester_user,
+            contact: header_value(request, "Contact").and_then(|c| uri_socket_addr(&c)),
+        };
+        let takeover = match self
+            .call_router
+            .take_over(takeover, Some((media.stream.clone(), media.format)))
+            .await
         {
-            let mut calls = self.active_calls.write().await;
-            if let Some(call) = calls.get_mut(&call_id) {
-                call.state = CallSessionState::Answered;
+            Ok(takeover) => takeover,
+            Err(e) => {
+                warn!("Takeover INVITE {} rejected: {}", call_id, e);
+                media.stream.close().await;
+                return ResponseBuilder::new(e.status_code()).build_for_request(request);
             }
+        };
+
+        if let Some(info) = self.call_router.get_active_call(&takeover.call_id).await {
+            let mut calls = self.active_calls.write().await;
+            let session = calls
+                .entry(takeover.call_id.clone())
+                .or_insert_with(|| CallSession {
+                    call_id: takeover.call_id.clone(),
+                    from_uri: info.caller_uri.clone(),
+                    to_uri: info.callee_uri.clone(),
+                    state: CallSessionState::Inviting,
+                    media_bridge: None,
+                });
+            session.to_uri = info.callee_uri;
+            session.state = CallSessionState::Answered;
+            session.media_bridge = takeover.bridge.clone();
         }
 
-        // Create SDP answer with negotiated codec
-        let sdp = SdpSession::create_audio_session(self.local_ip, local_port);
-        let sdp_body = sdp.to_string();
+        if let Some(releaser) = &self.leg_releaser {
+            let releaser = releaser.clone();
+            let call_id = takeover.call_id.clone();
+            let replaced = takeover.replaced.clone();
+            tokio::spawn(async move {
+                if let Err(e) = releaser.release(&call_id, &replaced).await {
+                    warn!(
+                        "Failed to release replaced device {} of call {}: {}",
+                        replaced.uri, call_id, e
+                    );
+                }
+            });
+        }
 
-        // Build 200 OK response with SDP
-        let response = ResponseBuilder::ok()
+        // The new device's dialog keeps its own offer/answer state
+        let sdp = self.negotiated_sdp(request, &media);
+        let offer_body = String::from_utf8_lossy(request.body()).to_string();
+        let cseq = request.cseq().unwrap_or(1);
+        let sdp_body = self
+            .call_router
+            .dialog_manager()
+            .with_dialog(&call_id, false, |d| d.answer_offer(cseq, &offer_body, sdp))
+            .await;
+
+        info!("Call {} taken over by INVITE {}", takeover.call_id, call_id);
+        ResponseBuilder::ok()
+            .to_tag(&Uuid::new_v4().simple().to_string())
             .body(sdp_body.into_bytes())
-            .build_for_request(request)?;
-
-        info!("Sent 200 OK for call {}", call_id);
-
-        Ok(response)
+            .build_for_request(request)
     }
 
     /// Handle re-INVITE for session modification (hold/resume)
@@ -322,8 +2013,31 @@ impl InviteHandler {
             }
         };
 
+        let cseq = request.cseq().unwrap_or(0);
+        let dialogs = self.call_router.dialog_manager();
+        // Differs from call_id when the sender took the call over
+        let dialog_id = request.call_id().unwrap_or_else(|| call_id.to_string());
+
+        // Retransmission or glare with our own outstanding re-INVITE
+        match dialogs
+            .with_dialog(&dialog_id, false, |d| d.classify_reinvite(cseq))
+            .await
+        {
+            ReinviteAction::Retransmission(answer) => {
+                debug!("Retransmitted re-INVITE for call {}, resending answer", call_id);
+                return ResponseBuilder::ok()
+                    .body(answer.into_bytes())
+                    .build_for_request(request);
+            }
+            ReinviteAction::Glare => {
+                info!("re-INVITE glare on call {}, sending 491", call_id);
+                return ResponseBuilder::new(491).build_for_request(request);
+            }
+            ReinviteAction::NewOffer => {}
+        }
+
         if let Some(offer) = sdp_offer {
-            let sdp_str = String::from_utf8_lossy(request.body());
+            let sdp_str = String::from_utf8_lossy(request.body()).to_string();
 
             // Detect hold state from SDP
             let hold_state = SdpHoldHelper::detect_hold_state(&sdp_str);
@@ -353,36 +2067,28 @@ impl InviteHandler {
                 }
             }
 
-            // Create SDP answer
-            // For now, we'll mirror the hold state back
+            // Create SDP answer; the dialog mirrors the offered direction
+            // and keeps the o= line stable across re-INVITEs
             // In a real implementation, you'd use the actual local media parameters
             let media_port = offer.audio_media().map(|m| m.port).unwrap_or(10000);
-            let sdp = SdpSession::create_audio_session(
-                self.local_ip,
+            // The call keeps its codec whatever the current profile
+            let mut payloads = offer.negotiate_audio(&self.codec_negotiator);
+            if let Some(codec) = self
+                .codec_negotiator
+                .capacity_monitor()
+                .and_then(|monitor| monitor.call_codec(call_id))
+            {
+                payloads.keep_primary(&codec);
+            }
+            let sdp = self.local_sdp(
+                self.advertised_media_ip(self.media_ip(Some(&offer)), request),
                 media_port,
+                &payloads,
+                None,
             );
-            let mut sdp_body = sdp.to_string();
-
-            // Apply hold state to answer SDP
-            match hold_state {
-                HoldState::Active => {
-                    // Remote is active, we're active (sendrecv)
-                    sdp_body = SdpHoldHelper::create_resume_sdp(&sdp_body);
-                }
-                HoldState::RemoteHold | HoldState::LocalHold => {
-                    // Remote is on hold, we send recvonly
-                    // (we're receiving only, they're sending music on hold)
-                    // Note: This is the opposite of their sendonly
-                    sdp_body = sdp_body.replace("a=sendrecv", "a=recvonly");
-                    if !sdp_body.contains("a=recvonly") {
-                        sdp_body.push_str("a=recvonly\r\n");
-                    }
-                }
-                HoldState::BothHold => {
-                    // Both on hold
-                    sdp_body = SdpHoldHelper::create_hold_sdp(&sdp_body, true);
-                }
-            }
+            let sdp_body = dialogs
+                .with_dialog(&dialog_id, false, |d| d.answer_offer(cseq, &sdp_str, sdp))
+                .await;
 
             // Build 200 OK response with SDP
             let response = ResponseBuilder::ok()
@@ -421,6 +2127,47 @@ impl InviteHandler {
     }
 }
 
+/// How long a ringing call waits for the callee before the caller gets 480
+const NO_ANSWER_TIMEOUT: Duration = Duration::from_secs(180);
+
+#[async_trait]
+impl TransactionUser for InviteHandler {
+    /// 100 Trying goes out right away; the INVITE of a call left ringing
+    /// is answered once the callee answers
+    async fn handle_transaction(
+        self: Arc<Self>,
+        transaction: TransactionHandle,
+    ) -> Result<TransactionAction, SipError> {
+        let request = transaction.request().clone();
+        transaction.send_provisional(ResponseBuilder::new(100).build_for_request(&request)?)?;
+
+        let response = self.handle_invite(&request).await?;
+        if response.status_code() >= 200 {
+            return Ok(TransactionAction::Respond(response));
+        }
+        transaction.send_provisional(response)?;
+
+        let answered = {
+            let handler = self.clone();
+            let request = request.clone();
+            async move { handler.await_answer(&request).await }
+        };
+        let router = self.call_router.clone();
+        let no_answer = async move {
+            let call_id = request.call_id().unwrap_or_default();
+            if let Err(e) = router.reject_call(&call_id, "No answer").await {
+                debug!("Unanswered call {} not rejected in router: {}", call_id, e);
+            }
+            ResponseBuilder::new(480).build_for_request(&request)
+        };
+        Ok(transaction.respond_when(answered, NO_ANSWER_TIMEOUT, no_answer))
+    }
+
+    fn can_handle(&self, method: SipMethod) -> bool {
+        matches!(method, SipMethod::Invite)
+    }
+}
+
 #[async_trait]
 impl SipHandler for InviteHandler {
     async fn handle_request(&self, request: SipRequest) -> Result<SipResponse, SipError> {
@@ -435,11 +2182,26 @@ impl SipHandler for InviteHandler {
 /// ACK handler
 pub struct AckHandler {
     active_calls: Arc<RwLock<HashMap<String, CallSession>>>,
+    call_router: Option<Arc<CallRouter>>,
 }
 
 impl AckHandler {
     pub fn new(active_calls: Arc<RwLock<HashMap<String, CallSession>>>) -> Self {
-        Self { active_calls }
+        Self {
+            active_calls,
+            call_router: None,
+        }
+    }
+
+    /// Create ACK handler recording answers as completed in the router
+    pub fn with_router(
+        active_calls: Arc<RwLock<HashMap<String, CallSession>>>,
+        call_router: Arc<CallRouter>,
+    ) -> Self {
+        Self {
+            active_calls,
+            call_router: Some(call_router),
+        }
     }
 }
 
@@ -451,9 +2213,14 @@ impl SipHandler for AckHandler {
 
         // ACK doesn't need a response (it's a response itself)
         // Just log it
-        let calls = self.active_calls.read().await;
-        if let Some(call) = calls.get(&call_id) {
-            info!("Call {} confirmed: {} -> {}", call_id, call.from_uri, call.to_uri);
+        {
+            let calls = self.active_calls.read().await;
+            if let Some(call) = calls.get(&call_id) {
+                info!("Call {} confirmed: {} -> {}", call_id, call.from_uri, call.to_uri);
+            }
+        }
+        if let Some(router) = &self.call_router {
+            router.acknowledge_call(&call_id).await;
         }
 
         // Return a dummy response (won't be sent)
@@ -500,8 +2267,8 @@ impl SipHandler for CancelHandler {
                     if let Some(call) = calls.remove(&call_id) {
                         // Stop media bridge if it exists
                         if let Some(bridge) = call.media_bridge {
-                            bridge.stop().await;
-                            debug!("Media bridge stopped for cancelled call {}", call_id);
+                            bridge.close().await;
+                            debug!("Media bridge closed for cancelled call {}", call_id);
                         }
                     }
                 }
@@ -532,6 +2299,7 @@ impl SipHandler for CancelHandler {
 pub struct ByeHandler {
     active_calls: Arc<RwLock<HashMap<String, CallSession>>>,
     call_router: Option<Arc<CallRouter>>,
+    surveys: Option<Arc<SurveyRunner>>,
 }
 
 impl ByeHandler {
@@ -539,6 +2307,7 @@ impl ByeHandler {
         Self {
             active_calls,
             call_router: None,
+            surveys: None,
         }
     }
 
@@ -547,18 +2316,48 @@ impl ByeHandler {
         Self {
             active_calls,
             call_router: Some(call_router),
+            surveys: None,
         }
     }
+
+    /// Keep callers of surveyed calls up when their agent hangs up
+    pub fn with_surveys(mut self, surveys: Arc<SurveyRunner>) -> Self {
+        self.surveys = Some(surveys);
+        self
+    }
 }
 
 #[async_trait]
 impl SipHandler for ByeHandler {
     async fn handle_request(&self, request: SipRequest) -> Result<SipResponse, SipError> {
-        let call_id = request.call_id().unwrap_or_else(|| "unknown".to_string());
+        let mut call_id = request.call_id().unwrap_or_else(|| "unknown".to_string());
         info!("Received BYE for call {}", call_id);
 
         // Terminate call in router
         if let Some(router) = &self.call_router {
+            // A device that took the call over hangs up the call it took
+            call_id = router.canonical_call_id(&call_id).await;
+
+            let from_uri = request
+                .headers()
+                .iter()
+                .find_map(|h| match h {
+                    Header::From(from) => from.uri().ok().map(|u| u.to_string()),
+                    _ => None,
+                })
+                .unwrap_or_default();
+
+            // A transferor hanging up leaves the transferee to the transfer
+            if router.release_transferor(&call_id, &from_uri).await {
+                return ResponseBuilder::ok().build_for_request(&request);
+            }
+            // An agent hanging up leaves the caller to the survey
+            if let Some(surveys) = &self.surveys {
+                if surveys.agent_left(&call_id, &from_uri).await {
+                    return ResponseBuilder::ok().build_for_request(&request);
+                }
+                surveys.caller_left(&call_id);
+            }
             if let Err(e) = router.terminate_call(&call_id).await {
                 warn!("Failed to terminate call in router: {}", e);
             }
@@ -572,8 +2371,8 @@ impl SipHandler for ByeHandler {
 
                 // Stop media bridge
                 if let Some(bridge) = call.media_bridge {
-                    bridge.stop().await;
-                    info!("Media bridge stopped for call {}", call_id);
+                    bridge.close().await;
+                    info!("Media bridge closed for call {}", call_id);
                 }
             }
         }
@@ -590,17 +2389,40 @@ impl SipHandler for ByeHandler {
 /// REFER handler - handles blind and attended transfers
 pub struct ReferHandler {
     call_router: Arc<CallRouter>,
+    /// Places the INVITE to the transfer target and recovery destination
+    forwarder: Option<Arc<dyn InviteForwarder>>,
+    /// Sends sipfrag NOTIFYs to the transferor
+    notifier: Option<Arc<dyn TransferNotifier>>,
 }
 
 impl ReferHandler {
     pub fn new(call_router: Arc<CallRouter>) -> Self {
-        Self { call_router }
+        Self {
+            call_router,
+            forwarder: None,
+            notifier: None,
+        }
+    }
+
+    /// Carry out accepted blind transfers (otherwise they are only recorded)
+    pub fn with_transfer_signaling(
+        mut self,
+        forwarder: Arc<dyn InviteForwarder>,
+        notifier: Arc<dyn TransferNotifier>,
+    ) -> Self {
+        self.forwarder = Some(forwarder);
+        self.notifier = Some(notifier);
+        self
     }
 }
 
 #[async_trait]
 impl SipHandler for ReferHandler {
     async fn handle_request(&self, request: SipRequest) -> Result<SipResponse, SipError> {
+        if matches!(request.method(), Some(SipMethod::Subscribe)) {
+            return self.handle_subscribe(&request).await;
+        }
+
         let call_id = request.call_id().unwrap_or_else(|| "unknown".to_string());
         info!("Received REFER for call {}", call_id);
 
@@ -620,7 +2442,7 @@ impl SipHandler for ReferHandler {
             return ResponseBuilder::new(400).build_for_request(&request);
         }
 
-        let refer_to_uri = refer_to.unwrap();
+        let refer_to_uri = refer_to_uri(&refer_to.unwrap());
         info!("Transfer target: {}", refer_to_uri);
 
         // Check if Replaces header exists (attended transfer)
@@ -644,16 +2466,54 @@ impl SipHandler for ReferHandler {
         } else {
             // Blind transfer
             info!("Blind transfer requested for call {}", call_id);
+            let from_uri = request
+                .headers()
+                .iter()
+                .find_map(|h| match h {
+                    Header::From(from) => from.uri().ok().map(|u| u.to_string()),
+                    _ => None,
+                })
+                .unwrap_or_default();
             self.call_router
-                .blind_transfer(&call_id, &refer_to_uri)
+                .blind_transfer_from(&call_id, &from_uri, &refer_to_uri)
                 .await
         };
 
         match transfer_result {
             Ok(_) => {
                 info!("Transfer initiated for call {}", call_id);
+                // RFC 4488: the transferor wants no implicit subscription
+                let refer_sub = header_value(&request, "Refer-Sub");
+                let no_subscription =
+                    refer_sub.is_some_and(|value| value.eq_ignore_ascii_case("false"));
+                if no_subscription {
+                    self.call_router.decline_refer_subscription(&call_id).await;
+                }
+                if let (None, Some(forwarder), Some(notifier)) =
+                    (&replaces, &self.forwarder, &self.notifier)
+                {
+                    let router = self.call_router.clone();
+                    let forwarder = forwarder.clone();
+                    let notifier = notifier.clone();
+                    let call_id = call_id.clone();
+                    tokio::spawn(async move {
+                        match router
+                            .execute_transfer(&call_id, forwarder.as_ref(), notifier.as_ref())
+                            .await
+                        {
+                            Ok(outcome) => info!("Transfer of call {} ended: {:?}", call_id, outcome),
+                            Err(e) => warn!("Transfer of call {} aborted: {}", call_id, e),
+                        }
+                    });
+                }
                 // Return 202 Accepted
-                ResponseBuilder::new(202).build_for_request(&request)
+                let accepted = ResponseBuilder::new(202);
+                let accepted = if no_subscription {
+                    accepted.header(Header::Other("Refer-Sub".into(), "false".into()))
+                } else {
+                    accepted
+                };
+                accepted.build_for_request(&request)
             }
             Err(e) => {
                 warn!("Transfer failed for call {}: {}", call_id, e);
@@ -664,21 +2524,83 @@ impl SipHandler for ReferHandler {
     }
 
     fn can_handle(&self, method: SipMethod) -> bool {
-        matches!(method, SipMethod::Refer)
+        matches!(method, SipMethod::Refer | SipMethod::Subscribe)
     }
 }
 
-/// NOTIFY handler - handles transfer status notifications
-pub struct NotifyHandler {
-    call_router: Arc<CallRouter>,
+impl ReferHandler {
+    /// Refresh (or end, with `Expires: 0`) the implicit subscription of a
+    /// REFER; the answering NOTIFY carries the last reported status
+    async fn handle_subscribe(&self, request: &SipRequest) -> Result<SipResponse, SipError> {
+        let call_id = request.call_id().unwrap_or_default();
+        let event = header_value(request, "Event").unwrap_or_default();
+        let package = event.split(';').next().unwrap_or_default().trim();
+        if !package.eq_ignore_ascii_case("refer") {
+            warn!("Unsupported event package: {}", event);
+            return ResponseBuilder::new(489).build_for_request(request);
+        }
+
+        let expires = header_value(request, "Expires")
+            .and_then(|v| v.parse::<u64>().ok())
+            .unwrap_or(self.call_router.transfer_policy().subscription_secs);
+        let (transferor_uri, notify) = match self
+            .call_router
+            .refresh_refer_subscription(&call_id, std::time::Duration::from_secs(expires))
+            .await
+        {
+            Ok(refreshed) => refreshed,
+            Err(e) => {
+                debug!("SUBSCRIBE to refer of call {} rejected: {}", call_id, e);
+                return ResponseBuilder::new(481).build_for_request(request);
+            }
+        };
+
+        if let Some(notifier) = self.notifier.clone() {
+            let call_id = call_id.clone();
+            tokio::spawn(async move {
+                if let Err(e) = notifier.notify(&call_id, &transferor_uri, &notify).await {
+                    warn!("Failed to notify transferor of call {}: {}", call_id, e);
+                }
+            });
+        }
+        ResponseBuilder::new(200)
+            .header(Header::Expires(expires.to_string().into()))
+            .build_for_request(request)
+    }
 }
 
-impl NotifyHandler {
-    pub fn new(call_router: Arc<CallRouter>) -> Self {
-        Self { call_router }
+/// Value of the first `name` header, typed by rsip or not
+/// Address the request came from: the top Via's `received`, else its
+/// sent-by host
+fn signaling_ip(request: &SipRequest) -> Option<IpAddr> {
+    let via = header_value(request, "Via")?;
+    if let Some(received) = extract_received_from_via(&via) {
+        return received.parse().ok();
     }
+    // "SIP/2.0/UDP host:port;params"
+    let sent_by = via.split(';').next()?.split_whitespace().nth(1)?;
+    let host = match sent_by.strip_prefix('[') {
+        Some(v6) => v6.split(']').next()?,
+        None => sent_by.split(':').next()?,
+    };
+    host.parse().ok()
 }
 
+fn header_value(request: &SipRequest, name: &str) -> Option<String> {
+    request.headers().iter().find_map(|h| {
+        let line = h.to_string();
+        let (header, value) = line.split_once(':')?;
+        header
+            .trim()
+            .eq_ignore_ascii_case(name)
+            .then(|| value.trim().to_string())
+    })
+}
+
+/// NOTIFY handler - handles transfer status notifications
+#[derive(Default)]
+pub struct NotifyHandler;
+
 #[async_trait]
 impl SipHandler for NotifyHandler {
     async fn handle_request(&self, request: SipRequest) -> Result<SipResponse, SipError> {
@@ -733,7 +2655,10 @@ impl SipHandler for NotifyHandler {
 #[cfg(test)]
 mod tests {
     use super::*;
-    use std::net::{IpAddr, Ipv4Addr};
+    use super::super::replaces::ReplacedLeg;
+    use crate::domain::cdr::{CallDetailRecord, MockCdrRepository};
+    use crate::test_support::{sdp_offer, SipRequestBuilder};
+    use std::net::{IpAddr, Ipv4Addr, SocketAddr};
 
     #[test]
     fn test_call_session_state() {
@@ -768,26 +2693,10 @@ mod tests {
         let call_router = invite_handler.call_router();
 
         // Create INVITE request from alice to bob
-        let invite_request = "INVITE sip:bob@example.com SIP/2.0\r\n\
-            Via: SIP/2.0/UDP 127.0.0.1:5060;branch=z9hG4bK776asdhds\r\n\
-            From: Alice <sip:alice@example.com>;tag=1928301774\r\n\
-            To: Bob <sip:bob@example.com>\r\n\
-            Call-ID: a84b4c76e66710\r\n\
-            CSeq: 314159 INVITE\r\n\
-            Contact: <sip:alice@127.0.0.1:5060>\r\n\
-            Content-Type: application/sdp\r\n\
-            Content-Length: 142\r\n\
-            \r\n\
-            v=0\r\n\
-            o=alice 2890844526 2890844526 IN IP4 127.0.0.1\r\n\
-            s=-\r\n\
-            c=IN IP4 127.0.0.1\r\n\
-            t=0 0\r\n\
-            m=audio 49170 RTP/AVP 0 8\r\n\
-            a=rtpmap:0 PCMU/8000\r\n\
-            a=rtpmap:8 PCMA/8000\r\n";
-
-        let request = SipRequest::parse(invite_request.as_bytes()).unwrap();
+        let invite = SipRequestBuilder::invite("sip:alice@example.com", "sip:bob@example.com")
+            .call_id("a84b4c76e66710")
+            .sdp(&sdp_offer(local_ip, 49170, &[(0, "PCMU/8000"), (8, "PCMA/8000")]));
+        let request = invite.build();
 
         // Handle INVITE
         let response = invite_handler.handle_request(request.clone()).await.unwrap();
@@ -817,15 +2726,7 @@ mod tests {
         );
 
         // Create BYE request
-        let bye_request = "BYE sip:bob@example.com SIP/2.0\r\n\
-            Via: SIP/2.0/UDP 127.0.0.1:5060;branch=z9hG4bK776asdhds\r\n\
-            From: Alice <sip:alice@example.com>;tag=1928301774\r\n\
-            To: Bob <sip:bob@example.com>\r\n\
-            Call-ID: a84b4c76e66710\r\n\
-            CSeq: 314160 BYE\r\n\
-            \r\n";
-
-        let bye_req = SipRequest::parse(bye_request.as_bytes()).unwrap();
+        let bye_req = invite.next_in_dialog(SipMethod::Bye).build();
 
         // Handle BYE
         let bye_response = bye_handler.handle_request(bye_req).await.unwrap();
@@ -838,6 +2739,99 @@ mod tests {
         assert_eq!(call_count_after, 0);
     }
 
+    #[tokio::test]
+    async fn test_invite_finds_binding_regardless_of_case_and_parameters() {
+        let registrar = Arc::new(Registrar::new());
+        let local_ip = IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1));
+        registrar
+            .add_binding("sip:Alice@Example.COM".to_string(), "sip:alice@127.0.0.1:5062".to_string(), 3600)
+            .await
+            .unwrap();
+        registrar
+            .add_binding("sip:bob@example.com".to_string(), "sip:bob@127.0.0.1:5061".to_string(), 3600)
+            .await
+            .unwrap();
+
+        let invite_handler = InviteHandler::new(registrar.clone(), local_ip);
+        let call_router = invite_handler.call_router();
+        let callee = "sip:alice@example.com;transport=udp";
+        assert_eq!(
+            call_router.find_callee_contact(callee).await,
+            Some(SocketAddr::new(local_ip, 5062))
+        );
+
+        let request = SipRequestBuilder::invite("sip:bob@example.com", callee)
+            .call_id("case-folded-aor")
+            .sdp(&sdp_offer(local_ip, 49170, &[(0, "PCMU/8000")]))
+            .build();
+        let response = invite_handler.handle_request(request).await.unwrap();
+        assert_eq!(response.status_code(), 200);
+        assert_eq!(call_router.active_call_count().await, 1);
+    }
+
+    #[tokio::test]
+    async fn test_reinvite_retransmission_and_glare() {
+        let registrar = Arc::new(Registrar::new());
+        let local_ip = IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1));
+
+        registrar.add_binding(
+            "sip:bob@example.com".to_string(),
+            "127.0.0.1:5061".to_string(),
+            3600,
+        ).await.unwrap();
+
+        let invite_handler = InviteHandler::new(registrar.clone(), local_ip);
+        let call_router = invite_handler.call_router();
+
+        let invite = |cseq: u32, direction: &str| {
+            let sdp = format!(
+                "v=0\r\no=alice 2890844526 2890844526 IN IP4 127.0.0.1\r\ns=-\r\n\
+                 c=IN IP4 127.0.0.1\r\nt=0 0\r\nm=audio 49170 RTP/AVP 0\r\n\
+                 a=rtpmap:0 PCMU/8000\r\na={}\r\n",
+                direction
+            );
+            let raw = format!(
+                "INVITE sip:bob@example.com SIP/2.0\r\n\
+                 Via: SIP/2.0/UDP 127.0.0.1:5060;branch=z9hG4bK-{cseq}\r\n\
+                 From: Alice <sip:alice@example.com>;tag=1928301774\r\n\
+                 To: Bob <sip:bob@example.com>\r\n\
+                 Call-ID: reinvite-glare-test\r\n\
+                 CSeq: {cseq} INVITE\r\n\
+                 Content-Type: application/sdp\r\n\
+                 Content-Length: {}\r\n\r\n{sdp}",
+                sdp.len()
+            );
+            SipRequest::parse(raw.as_bytes()).unwrap()
+        };
+
+        let response = invite_handler.handle_request(invite(1, "sendrecv")).await.unwrap();
+        assert_eq!(response.status_code(), 200);
+        let initial = SdpSession::parse(&String::from_utf8_lossy(response.body())).unwrap();
+
+        // Remote hold: answer is recvonly with the same session id
+        let hold = invite_handler.handle_request(invite(2, "sendonly")).await.unwrap();
+        assert_eq!(hold.status_code(), 200);
+        let answer = SdpSession::parse(&String::from_utf8_lossy(hold.body())).unwrap();
+        assert_eq!(answer.audio_direction(), StreamDirection::RecvOnly);
+        assert_eq!(answer.origin.session_id, initial.origin.session_id);
+        assert_eq!(answer.origin.session_version, "2");
+
+        // Retransmission gets a byte-identical answer
+        let retransmit = invite_handler.handle_request(invite(2, "sendonly")).await.unwrap();
+        assert_eq!(retransmit.body(), hold.body());
+
+        // Our own re-INVITE is outstanding: a new remote one gets 491
+        call_router
+            .dialog_manager()
+            .with_dialog("reinvite-glare-test", false, |d| {
+                d.start_reinvite(SdpSession::create_audio_session(local_ip, 10000))
+            })
+            .await
+            .unwrap();
+        let glare = invite_handler.handle_request(invite(3, "sendrecv")).await.unwrap();
+        assert_eq!(glare.status_code(), 491);
+    }
+
     #[tokio::test]
     async fn test_call_forwarding_not_found() {
         // Setup
@@ -855,14 +2849,9 @@ mod tests {
         let invite_handler = InviteHandler::new(registrar.clone(), local_ip);
 
         // Create INVITE request to unregistered user
-        let invite_request = "INVITE sip:bob@example.com SIP/2.0\r\n\
-            From: Alice <sip:alice@example.com>;tag=1928301774\r\n\
-            To: Bob <sip:bob@example.com>\r\n\
-            Call-ID: test-not-found\r\n\
-            CSeq: 1 INVITE\r\n\
-            \r\n";
-
-        let request = SipRequest::parse(invite_request.as_bytes()).unwrap();
+        let request = SipRequestBuilder::invite("sip:alice@example.com", "sip:bob@example.com")
+            .call_id("test-not-found")
+            .build();
 
         // Handle INVITE
         let response = invite_handler.handle_request(request).await.unwrap();
@@ -895,14 +2884,9 @@ mod tests {
         invite_handler.set_auto_answer(false);
 
         // Create INVITE request
-        let invite_request = "INVITE sip:bob@example.com SIP/2.0\r\n\
-            From: Alice <sip:alice@example.com>;tag=1928301774\r\n\
-            To: Bob <sip:bob@example.com>\r\n\
-            Call-ID: test-ringing\r\n\
-            CSeq: 1 INVITE\r\n\
-            \r\n";
-
-        let request = SipRequest::parse(invite_request.as_bytes()).unwrap();
+        let request = SipRequestBuilder::invite("sip:alice@example.com", "sip:bob@example.com")
+            .call_id("test-ringing")
+            .build();
 
         // Handle INVITE
         let response = invite_handler.handle_request(request).await.unwrap();
@@ -916,6 +2900,90 @@ mod tests {
         assert_eq!(call_state, Some(super::super::call_state::CallState::Ringing));
     }
 
+    #[tokio::test]
+    async fn test_unsupported_codec_releases_media() {
+        let registrar = Arc::new(Registrar::new());
+        let local_ip = IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1));
+
+        registrar.add_binding(
+            "sip:bob@example.com".to_string(),
+            "127.0.0.1:5061".to_string(),
+            3600,
+        ).await.unwrap();
+
+        let allocator = Arc::new(RtpPortAllocator::new(32000, 32010));
+        let invite_handler = InviteHandler::new(registrar.clone(), local_ip)
+            .with_port_allocator(allocator.clone());
+        let call_router = invite_handler.call_router();
+
+        // G.729 only: nothing we can answer with
+        let request = SipRequestBuilder::invite("sip:alice@example.com", "sip:bob@example.com")
+            .call_id("test-no-codec")
+            .sdp(&sdp_offer(local_ip, 49170, &[(18, "G729/8000")]))
+            .build();
+        let response = invite_handler.handle_request(request).await.unwrap();
+        assert_eq!(response.status_code(), 488);
+
+        // Ports, tasks and call state are all gone
+        assert_eq!(allocator.in_use(), 0);
+        assert_eq!(allocator.live_tasks(), 0);
+        assert_eq!(call_router.active_call_count().await, 0);
+        assert!(invite_handler.active_calls.read().await.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_answer_keeps_offered_payload_types() {
+        let registrar = Arc::new(Registrar::new());
+        let local_ip = IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1));
+
+        registrar.add_binding(
+            "sip:bob@example.com".to_string(),
+            "127.0.0.1:5061".to_string(),
+            3600,
+        ).await.unwrap();
+
+        let allocator = Arc::new(RtpPortAllocator::new(32020, 32030));
+        let invite_handler = InviteHandler::new(registrar.clone(), local_ip)
+            .with_port_allocator(allocator);
+
+        // Opus and telephone-event on the offerer's dynamic payload types,
+        // sent as a one-way stream
+        let invite_request = "INVITE sip:bob@example.com SIP/2.0\r\n\
+            Via: SIP/2.0/UDP 127.0.0.1:5060;branch=z9hG4bK96te\r\n\
+            From: Alice <sip:alice@example.com>;tag=96te\r\n\
+            To: Bob <sip:bob@example.com>\r\n\
+            Call-ID: test-dynamic-pt\r\n\
+            CSeq: 1 INVITE\r\n\
+            Contact: <sip:alice@127.0.0.1:5060>\r\n\
+            Content-Type: application/sdp\r\n\
+            \r\n\
+            v=0\r\n\
+            o=alice 2890844526 2890844526 IN IP4 127.0.0.1\r\n\
+            s=-\r\n\
+            c=IN IP4 127.0.0.1\r\n\
+            t=0 0\r\n\
+            m=audio 49170 RTP/AVP 98 18 0 96\r\n\
+            a=rtpmap:98 opus/48000/2\r\n\
+            a=fmtp:98 useinbandfec=1\r\n\
+            a=rtpmap:18 G729/8000\r\n\
+            a=rtpmap:96 telephone-event/8000\r\n\
+            a=fmtp:96 0-16\r\n\
+            a=sendonly\r\n";
+
+        let request = SipRequest::parse(invite_request.as_bytes()).unwrap();
+        let response = invite_handler.handle_request(request).await.unwrap();
+        assert_eq!(response.status_code(), 200);
+
+        let answer = SdpSession::parse(&String::from_utf8_lossy(response.body())).unwrap();
+        let audio = answer.audio_media().unwrap();
+        assert_eq!(audio.formats, vec!["98", "0", "96"]);
+        assert_eq!(audio.encoding_of("98"), Some("opus/48000/2"));
+        assert_eq!(audio.encoding_of("96"), Some("telephone-event/8000"));
+        assert!(audio.attributes.contains(&"fmtp:98 useinbandfec=1".to_string()));
+        assert!(audio.attributes.contains(&"fmtp:96 0-16".to_string()));
+        assert_eq!(audio.direction, StreamDirection::RecvOnly);
+    }
+
     #[tokio::test]
     async fn test_cancel_ringing_call() {
         // Setup
@@ -1060,4 +3128,708 @@ mod tests {
         let call_state = call_router.get_call_state("test-cancel-established").await;
         assert_eq!(call_state, Some(super::super::call_state::CallState::Established));
     }
+
+    /// Records the devices it was asked to release
+    #[derive(Default)]
+    struct RecordingReleaser {
+        released: std::sync::Mutex<Vec<(String, ReplacedLeg)>>,
+    }
+
+    #[async_trait]
+    impl LegReleaser for RecordingReleaser {
+        async fn release(&self, call_id: &str, leg: &ReplacedLeg) -> Result<(), SipError> {
+            self.released
+                .lock()
+                .unwrap()
+                .push((call_id.to_string(), leg.clone()));
+            Ok(())
+        }
+    }
+
+    impl RecordingReleaser {
+        /// Releases so far, waiting briefly for the spawned one
+        async fn released(&self) -> Vec<(String, ReplacedLeg)> {
+            for _ in 0..50 {
+                if !self.released.lock().unwrap().is_empty() {
+                    break;
+                }
+                tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+            }
+            self.released.lock().unwrap().clone()
+        }
+    }
+
+    /// CDR repository keeping records in memory
+    fn cdr_store() -> (Arc<std::sync::Mutex<Vec<CallDetailRecord>>>, MockCdrRepository) {
+        let store = Arc::new(std::sync::Mutex::new(Vec::<CallDetailRecord>::new()));
+        let mut repo = MockCdrRepository::new();
+        let created = store.clone();
+        repo.expect_create().returning(move |cdr| {
+            created.lock().unwrap().push(cdr.clone());
+            Ok(())
+        });
+        let found = store.clone();
+        repo.expect_get_by_id()
+            .returning(move |id| Ok(found.lock().unwrap().iter().find(|c| c.id == id).cloned()));
+        let updated = store.clone();
+        repo.expect_update().returning(move |cdr| {
+            if let Some(stored) = updated.lock().unwrap().iter_mut().find(|c| c.id == cdr.id) {
+                *stored = cdr.clone();
+            }
+            Ok(())
+        });
+        (store, repo)
+    }
+
+    async fn register_alice_and_bob() -> Arc<Registrar> {
+        let registrar = Arc::new(Registrar::new());
+        for (uri, contact) in [
+            ("sip:alice@example.com", "127.0.0.1:5060"),
+            ("sip:bob@example.com", "127.0.0.1:5061"),
+        ] {
+            registrar
+                .add_binding(uri.to_string(), contact.to_string(), 3600)
+                .await
+                .unwrap();
+        }
+        registrar
+    }
+
+    /// INVITE from alice to bob, call `call_id`
+    fn alice_invite(call_id: &str, with_sdp: bool) -> SipRequest {
+        let body = if with_sdp { TAKEOVER_SDP } else { "" };
+        let invite = format!(
+            "INVITE sip:bob@example.com SIP/2.0\r\n\
+            Via: SIP/2.0/UDP 127.0.0.1:5060;branch=z9hG4bKalice\r\n\
+            From: Alice <sip:alice@example.com>;tag=1928301774\r\n\
+            To: Bob <sip:bob@example.com>\r\n\
+            Call-ID: {}\r\n\
+            CSeq: 1 INVITE\r\n\
+            Contact: <sip:alice@127.0.0.1:5060>\r\n\
+            Content-Type: application/sdp\r\n\
+            Content-Length: {}\r\n\
+            \r\n{}",
+            call_id,
+            body.len(),
+            body
+        );
+        SipRequest::parse(invite.as_bytes()).unwrap()
+    }
+
+    /// INVITE from `from` at `contact` replacing `replaces`
+    fn takeover_invite(call_id: &str, from: &str, contact: &str, replaces: &str) -> SipRequest {
+        let user = CallRouter::extract_username(from);
+        let invite = format!(
+            "INVITE sip:alice@example.com SIP/2.0\r\n\
+            Via: SIP/2.0/UDP {contact};branch=z9hG4bKtakeover\r\n\
+            From: <{from}>;tag=takeover\r\n\
+            To: Alice <sip:alice@example.com>\r\n\
+            Call-ID: {call_id}\r\n\
+            CSeq: 1 INVITE\r\n\
+            Contact: <sip:{user}@{contact}>\r\n\
+            Replaces: {replaces}\r\n\
+            Content-Type: application/sdp\r\n\
+            Content-Length: {}\r\n\
+            \r\n{TAKEOVER_SDP}",
+            TAKEOVER_SDP.len()
+        );
+        SipRequest::parse(invite.as_bytes()).unwrap()
+    }
+
+    const TAKEOVER_SDP: &str = "v=0\r\n\
+        o=phone 2890844526 2890844526 IN IP4 127.0.0.1\r\n\
+        s=-\r\n\
+        c=IN IP4 127.0.0.1\r\n\
+        t=0 0\r\n\
+        m=audio 49170 RTP/AVP 0 8\r\n\
+        a=rtpmap:0 PCMU/8000\r\n\
+        a=rtpmap:8 PCMA/8000\r\n";
+
+    #[tokio::test]
+    async fn test_takeover_of_confirmed_call_by_same_user() {
+        let registrar = register_alice_and_bob().await;
+        let (cdrs, cdr_repository) = cdr_store();
+        let releaser = Arc::new(RecordingReleaser::default());
+        let invite_handler = InviteHandler::new(registrar, IpAddr::V4(Ipv4Addr::LOCALHOST))
+            .with_cdr_repository(Arc::new(cdr_repository))
+            .with_takeover_signaling(releaser.clone());
+        let call_router = invite_handler.call_router();
+
+        let response = invite_handler
+            .handle_request(alice_invite("shared-line", true))
+            .await
+            .unwrap();
+        assert_eq!(response.status_code(), 200);
+        let local_tag = response.to_tag().unwrap();
+        call_router
+            .set_callee_contact("shared-line", "127.0.0.1:5061".parse().unwrap())
+            .await;
+
+        // Bob moves the call from his desk phone to his softphone
+        let replaces = format!("shared-line;to-tag={};from-tag=1928301774", local_tag);
+        let response = invite_handler
+            .handle_request(takeover_invite(
+                "softphone-1",
+                "sip:bob@example.com",
+                "127.0.0.1:5071",
+                &replaces,
+            ))
+            .await
+            .unwrap();
+        assert_eq!(response.status_code(), 200);
+        assert!(response.to_tag().is_some());
+
+        // Alice never noticed
+        assert_eq!(
+            call_router.get_call_state("shared-line").await,
+            Some(super::super::call_state::CallState::Established)
+        );
+        assert_eq!(
+            call_router.get_callee_contact("shared-line").await,
+            Some("127.0.0.1:5071".parse().unwrap())
+        );
+
+        // The desk phone is hung up
+        let released = releaser.released().await;
+        assert_eq!(released.len(), 1);
+        assert_eq!(released[0].0, "shared-line");
+        assert_eq!(released[0].1.contact, Some("127.0.0.1:5061".parse().unwrap()));
+        assert_eq!(released[0].1.release_method(), SipMethod::Bye);
+
+        // The desk phone's part of the call is a CDR leg of its own
+        {
+            let cdrs = cdrs.lock().unwrap();
+            assert_eq!(cdrs.len(), 2);
+            let leg = cdrs
+                .iter()
+                .find(|cdr| cdr.end_reason.as_deref() == Some("Replaced by sip:bob@example.com"))
+                .unwrap();
+            assert_eq!(leg.callee_username, "bob");
+            assert_eq!(leg.callee_ip.as_deref(), Some("127.0.0.1"));
+            assert_eq!(leg.correlation_id, cdrs[0].correlation_id);
+            assert_eq!(leg.status, crate::domain::cdr::CallStatus::Completed);
+        }
+
+        // The softphone's BYE, in its own dialog, ends the call
+        let bye_handler =
+            ByeHandler::with_router(invite_handler.active_calls.clone(), call_router.clone());
+        let bye = SipRequest::parse(
+            "BYE sip:alice@example.com SIP/2.0\r\n\
+            From: <sip:bob@example.com>;tag=takeover\r\n\
+            To: Alice <sip:alice@example.com>\r\n\
+            Call-ID: softphone-1\r\n\
+            CSeq: 2 BYE\r\n\
+            \r\n"
+                .as_bytes(),
+        )
+        .unwrap();
+        let response = bye_handler.handle_request(bye).await.unwrap();
+        assert_eq!(response.status_code(), 200);
+        assert_eq!(call_router.active_call_count().await, 0);
+        assert!(invite_handler.active_calls.read().await.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_early_takeover_picks_up_ringing_call() {
+        let registrar = register_alice_and_bob().await;
+        let mut policy = TakeoverPolicy::default();
+        policy
+            .permissions
+            .insert("reception".to_string(), vec!["*".to_string()]);
+        let releaser = Arc::new(RecordingReleaser::default());
+        let mut invite_handler = InviteHandler::new(registrar, IpAddr::V4(Ipv4Addr::LOCALHOST))
+            .with_takeover_policy(policy)
+            .with_takeover_signaling(releaser.clone());
+        invite_handler.set_auto_answer(false);
+        let call_router = invite_handler.call_router();
+
+        let response = invite_handler
+            .handle_request(alice_invite("ringing-call", false))
+            .await
+            .unwrap();
+        assert_eq!(response.status_code(), 180);
+        let local_tag = response.to_tag().unwrap();
+
+        // The attendant console picks up the call ringing at bob
+        let replaces = format!("ringing-call;to-tag={};from-tag=1928301774;early-only", local_tag);
+        let response = invite_handler
+            .handle_request(takeover_invite(
+                "console-1",
+                "sip:reception@example.com",
+                "127.0.0.1:5080",
+                &replaces,
+            ))
+            .await
+            .unwrap();
+        assert_eq!(response.status_code(), 200);
+
+        assert_eq!(
+            call_router.get_call_state("ringing-call").await,
+            Some(super::super::call_state::CallState::Established)
+        );
+        let call = call_router.get_active_call("ringing-call").await.unwrap();
+        assert_eq!(call.callee_uri, "sip:reception@example.com");
+
+        // Bob's phone stops ringing
+        let released = releaser.released().await;
+        assert_eq!(released.len(), 1);
+        assert_eq!(released[0].1.uri, "sip:bob@example.com");
+        assert_eq!(released[0].1.release_method(), SipMethod::Cancel);
+    }
+
+    #[tokio::test]
+    async fn test_takeover_by_third_party_is_forbidden() {
+        let registrar = register_alice_and_bob().await;
+        let allocator = Arc::new(RtpPortAllocator::new(32100, 32110));
+        let releaser = Arc::new(RecordingReleaser::default());
+        let invite_handler = InviteHandler::new(registrar, IpAddr::V4(Ipv4Addr::LOCALHOST))
+            .with_port_allocator(allocator.clone())
+            .with_takeover_signaling(releaser.clone());
+        let call_router = invite_handler.call_router();
+
+        let response = invite_handler
+            .handle_request(alice_invite("private-call", true))
+            .await
+            .unwrap();
+        let local_tag = response.to_tag().unwrap();
+        call_router
+            .set_callee_contact("private-call", "127.0.0.1:5061".parse().unwrap())
+            .await;
+
+        // Carol may not take over calls of alice or bob
+        let replaces = format!("private-call;to-tag={};from-tag=1928301774", local_tag);
+        let response = invite_handler
+            .handle_request(takeover_invite(
+                "carol-1",
+                "sip:carol@example.com",
+                "127.0.0.1:5090",
+                &replaces,
+            ))
+            .await
+            .unwrap();
+        assert_eq!(response.status_code(), 403);
+
+        // Nothing changed and carol's media was released
+        let call = call_router.get_active_call("private-call").await.unwrap();
+        assert_eq!(call.state, "Established");
+        assert_eq!(call.callee_contact.as_deref(), Some("127.0.0.1:5061"));
+        assert_eq!(allocator.in_use(), 1);
+        assert!(releaser.released.lock().unwrap().is_empty());
+
+        // A Replaces naming the wrong dialog matches no call
+        let response = invite_handler
+            .handle_request(takeover_invite(
+                "bob-2",
+                "sip:bob@example.com",
+                "127.0.0.1:5071",
+                "private-call;to-tag=other;from-tag=1928301774",
+            ))
+            .await
+            .unwrap();
+        assert_eq!(response.status_code(), 481);
+    }
+
+    #[tokio::test]
+    async fn test_exhausted_rtp_ports_reused_after_bye() {
+        let registrar = register_alice_and_bob().await;
+        // A single pair
+        let allocator = Arc::new(RtpPortAllocator::new(32500, 32501));
+        let invite_handler = InviteHandler::new(registrar, IpAddr::V4(Ipv4Addr::LOCALHOST))
+            .with_port_allocator(allocator.clone());
+        let call_router = invite_handler.call_router();
+
+        let response = invite_handler
+            .handle_request(alice_invite("rtp-1", true))
+            .await
+            .unwrap();
+        assert_eq!(response.status_code(), 200);
+        assert!(allocator.is_allocated(32500));
+
+        // No pair left for a second call
+        let response = invite_handler
+            .handle_request(alice_invite("rtp-2", true))
+            .await
+            .unwrap();
+        assert_eq!(response.status_code(), 503);
+        assert_eq!(allocator.in_use(), 1);
+
+        // Hanging up returns the pair, which the next call gets
+        let bye_handler =
+            ByeHandler::with_router(invite_handler.active_calls.clone(), call_router.clone());
+        let bye = SipRequest::parse(
+            "BYE sip:bob@example.com SIP/2.0\r\n\
+            From: Alice <sip:alice@example.com>;tag=1928301774\r\n\
+            To: Bob <sip:bob@example.com>\r\n\
+            Call-ID: rtp-1\r\n\
+            CSeq: 2 BYE\r\n\
+            \r\n"
+                .as_bytes(),
+        )
+        .unwrap();
+        let response = bye_handler.handle_request(bye).await.unwrap();
+        assert_eq!(response.status_code(), 200);
+        assert_eq!(allocator.in_use(), 0);
+
+        let response = invite_handler
+            .handle_request(alice_invite("rtp-3", true))
+            .await
+            .unwrap();
+        assert_eq!(response.status_code(), 200);
+        assert!(allocator.is_allocated(32500));
+    }
+
+    /// Test services with the echo test limited to `max_concurrent` calls
+    /// of at most `max_duration_secs`, echoing after `delay_ms`
+    fn echo_service(
+        max_duration_secs: u64,
+        max_concurrent: usize,
+        delay_ms: u64,
+    ) -> Arc<InternalServiceHandler> {
+        use super::super::internal_services::{InternalServicesConfig, ServiceLimits};
+
+        Arc::new(InternalServiceHandler::new(InternalServicesConfig {
+            enabled: true,
+            echo: ServiceLimits {
+                max_duration_secs,
+                max_concurrent,
+            },
+            echo_delay_ms: delay_ms,
+            ..Default::default()
+        }))
+    }
+
+    /// INVITE from alice to extension `to`, offering PCMU at `rtp`
+    fn service_invite(call_id: &str, to: &str, rtp: SocketAddr) -> SipRequest {
+        let sdp = format!(
+            "v=0\r\n\
+            o=phone 2890844526 2890844526 IN IP4 {ip}\r\n\
+            s=-\r\n\
+            c=IN IP4 {ip}\r\n\
+            t=0 0\r\n\
+            m=audio {port} RTP/AVP 0\r\n\
+            a=rtpmap:0 PCMU/8000\r\n",
+            ip = rtp.ip(),
+            port = rtp.port()
+        );
+        let invite = format!(
+            "INVITE sip:{to}@example.com SIP/2.0\r\n\
+            Via: SIP/2.0/UDP 127.0.0.1:5060;branch=z9hG4bK{call_id}\r\n\
+            From: Alice <sip:alice@example.com>;tag=1928301774\r\n\
+            To: <sip:{to}@example.com>\r\n\
+            Call-ID: {call_id}\r\n\
+            CSeq: 1 INVITE\r\n\
+            Contact: <sip:alice@127.0.0.1:5060>\r\n\
+            Content-Type: application/sdp\r\n\
+            Content-Length: {}\r\n\
+            \r\n{sdp}",
+            sdp.len()
+        );
+        SipRequest::parse(invite.as_bytes()).unwrap()
+    }
+
+    /// Local address of the media answered in a 200 OK
+    fn answered_media(response: &SipResponse) -> SocketAddr {
+        let answer = SdpSession::parse(&String::from_utf8_lossy(response.body())).unwrap();
+        answer.audio_rtp_address().unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_echo_service_returns_payloads_after_delay() {
+        use crate::infrastructure::media::RtpPacket;
+        use std::time::{Duration, Instant};
+
+        // Nobody is registered: the service answers by itself
+        let (cdrs, cdr_repository) = cdr_store();
+        let services = echo_service(30, 2, 80);
+        let invite_handler =
+            InviteHandler::new(Arc::new(Registrar::new()), IpAddr::V4(Ipv4Addr::LOCALHOST))
+                .with_port_allocator(Arc::new(RtpPortAllocator::new(32200, 32210)))
+                .with_cdr_repository(Arc::new(cdr_repository))
+                .with_internal_services(services.clone());
+        let call_router = invite_handler.call_router();
+
+        let phone = tokio::net::UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let response = invite_handler
+            .handle_request(service_invite("echo-1", "9196", phone.local_addr().unwrap()))
+            .await
+            .unwrap();
+        assert_eq!(response.status_code(), 200);
+        assert_eq!(services.active_calls(InternalService::Echo), 1);
+        let echo = answered_media(&response);
+
+        let mut buf = [0u8; 2048];
+        for sequence in 0..3u16 {
+            let payload = bytes::Bytes::from(vec![sequence as u8 + 1; 160]);
+            let packet = RtpPacket::new(0, sequence, sequence as u32 * 160, 0x1234, payload);
+            let sent = Instant::now();
+            phone.send_to(&packet.serialize(), echo).await.unwrap();
+
+            let (len, _) = tokio::time::timeout(Duration::from_secs(2), phone.recv_from(&mut buf))
+                .await
+                .expect("no echo")
+                .unwrap();
+            let echoed = RtpPacket::parse(&buf[..len]).unwrap();
+            assert_eq!(echoed.payload, packet.payload);
+            assert_eq!(echoed.timestamp, packet.timestamp);
+            let delay = sent.elapsed();
+            assert!(delay >= Duration::from_millis(80), "echoed after {:?}", delay);
+            assert!(delay < Duration::from_millis(1000), "echoed after {:?}", delay);
+        }
+
+        {
+            let cdrs = cdrs.lock().unwrap();
+            assert_eq!(cdrs.len(), 1);
+            assert_eq!(cdrs[0].callee_username, "9196");
+            assert!(cdrs[0].test_call);
+        }
+
+        // Hanging up stops the service and frees its place
+        let bye_handler =
+            ByeHandler::with_router(invite_handler.active_calls.clone(), call_router.clone());
+        let bye = SipRequest::parse(
+            "BYE sip:9196@example.com SIP/2.0\r\n\
+            From: Alice <sip:alice@example.com>;tag=1928301774\r\n\
+            To: <sip:9196@example.com>\r\n\
+            Call-ID: echo-1\r\n\
+            CSeq: 2 BYE\r\n\
+            \r\n"
+                .as_bytes(),
+        )
+        .unwrap();
+        let response = bye_handler.handle_request(bye).await.unwrap();
+        assert_eq!(response.status_code(), 200);
+        for _ in 0..50 {
+            if services.active_calls(InternalService::Echo) == 0 {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+        assert_eq!(services.active_calls(InternalService::Echo), 0);
+    }
+
+    #[tokio::test]
+    async fn test_echo_service_caps_calls_and_duration() {
+        use std::time::Duration;
+
+        let services = echo_service(1, 1, 0);
+        let invite_handler =
+            InviteHandler::new(Arc::new(Registrar::new()), IpAddr::V4(Ipv4Addr::LOCALHOST))
+                .with_port_allocator(Arc::new(RtpPortAllocator::new(32300, 32310)))
+                .with_internal_services(services.clone());
+        let call_router = invite_handler.call_router();
+        let phone = "127.0.0.1:49170".parse().unwrap();
+
+        let response = invite_handler
+            .handle_request(service_invite("echo-cap-1", "9196", phone))
+            .await
+            .unwrap();
+        assert_eq!(response.status_code(), 200);
+
+        // Only one echo test call at a time
+        let response = invite_handler
+            .handle_request(service_invite("echo-cap-2", "9196", phone))
+            .await
+            .unwrap();
+        assert_eq!(response.status_code(), 486);
+        assert!(call_router.get_call_state("echo-cap-2").await.is_none());
+
+        // The first call is ended at the maximum duration
+        assert!(call_router.get_call_state("echo-cap-1").await.is_some());
+        for _ in 0..40 {
+            if call_router.get_call_state("echo-cap-1").await.is_none() {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(100)).await;
+        }
+        assert!(call_router.get_call_state("echo-cap-1").await.is_none());
+        assert_eq!(services.active_calls(InternalService::Echo), 0);
+
+        // Which frees the place for the next one
+        let response = invite_handler
+            .handle_request(service_invite("echo-cap-3", "9196", phone))
+            .await
+            .unwrap();
+        assert_eq!(response.status_code(), 200);
+        services.release("echo-cap-3");
+        call_router.discard_call("echo-cap-3").await;
+    }
+
+    #[tokio::test]
+    async fn test_feature_codes_before_routing() {
+        use crate::domain::dnd::DndManager;
+        use crate::domain::feature_code::FeatureCodeConfig;
+        use std::time::Duration;
+
+        let dnd = Arc::new(DndManager::new());
+        let config = FeatureCodeConfig {
+            announcement_secs: 0,
+            ..Default::default()
+        };
+        let registry = Arc::new(FeatureCodeRegistry::standard(config, dnd.clone()).unwrap());
+        let invite_handler =
+            InviteHandler::new(register_alice_and_bob().await, IpAddr::V4(Ipv4Addr::LOCALHOST))
+                .with_port_allocator(Arc::new(RtpPortAllocator::new(32400, 32410)))
+                .with_feature_codes(registry);
+        let call_router = invite_handler.call_router();
+        let phone: SocketAddr = "127.0.0.1:40000".parse().unwrap();
+
+        // *78 turns DND on and answers with the confirmation, then hangs up
+        let response = invite_handler
+            .handle_request(service_invite("feature-dnd", "*78", phone))
+            .await
+            .unwrap();
+        assert_eq!(response.status_code(), 200);
+        assert!(dnd.is_enabled("alice"));
+        for _ in 0..20 {
+            if call_router.get_call_state("feature-dnd").await.is_none() {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(50)).await;
+        }
+        assert!(call_router.get_call_state("feature-dnd").await.is_none());
+
+        // Unknown star codes hear an announcement instead of a 404
+        let response = invite_handler
+            .handle_request(service_invite("feature-unknown", "*42", phone))
+            .await
+            .unwrap();
+        assert_eq!(response.status_code(), 200);
+
+        // Without one configured they get the 404
+        let registry = FeatureCodeRegistry::new(FeatureCodeConfig {
+            unknown_code_announcement: None,
+            ..Default::default()
+        });
+        let invite_handler =
+            InviteHandler::new(register_alice_and_bob().await, IpAddr::V4(Ipv4Addr::LOCALHOST))
+                .with_feature_codes(Arc::new(registry));
+        let response = invite_handler
+            .handle_request(service_invite("feature-404", "*42", phone))
+            .await
+            .unwrap();
+        assert_eq!(response.status_code(), 404);
+    }
+
+    #[tokio::test]
+    async fn test_routing_simulator_traces_speed_dial_to_ported_number() {
+        use super::super::header_rules::{HeaderRule, HeaderRules, HeaderRulesConfig, RouteHeaderRules};
+        use crate::domain::routing::{
+            RouteOutcome, RoutedCall, RoutingLookup, RoutingOverride, TraceStep,
+        };
+        use crate::domain::shared::NumberingPlan;
+        use crate::domain::speed_dial::{MockSpeedDialRepository, SpeedDial};
+        use crate::domain::user::repository::MockUserRepository;
+        use crate::infrastructure::lnp::LnpConfig;
+        use chrono::{TimeZone, Utc};
+
+        struct Ported;
+
+        #[async_trait]
+        impl RoutingLookup for Ported {
+            async fn lookup(&self, number: &str) -> Result<RoutingOverride, String> {
+                Ok(RoutingOverride {
+                    routing_number: (number == "+14155551234").then(|| "+14155550000".to_string()),
+                    trunk: Some("carrier".to_string()),
+                })
+            }
+        }
+
+        // Company short code *1 for a number entered in national format
+        let mut repository = MockSpeedDialRepository::new();
+        repository.expect_list_company().returning(|| {
+            Ok(vec![SpeedDial::new(
+                None,
+                "*1".to_string(),
+                "4155551234".to_string(),
+                Some("Head office".to_string()),
+            )])
+        });
+        let mut users = MockUserRepository::new();
+        users.expect_find_by_username().returning(|_| Ok(None));
+        let numbering = Arc::new(NumberingPlan::default());
+        let speed_dials = SpeedDialService::new(Arc::new(repository), Arc::new(users))
+            .with_numbering_plan(numbering.clone());
+
+        let lnp = LnpResolver::new(&LnpConfig::default(), Arc::new(Ported), NumberingPlan::default());
+        let mut header_rules = HeaderRulesConfig::default();
+        header_rules.routes.push(RouteHeaderRules {
+            name: "north-america".to_string(),
+            pattern: "+1*".to_string(),
+            rules: HeaderRules {
+                outbound: vec![HeaderRule::Add {
+                    name: "X-Route".to_string(),
+                    value: "nanp".to_string(),
+                }],
+                ..Default::default()
+            },
+        });
+
+        let invite_handler =
+            InviteHandler::new(Arc::new(Registrar::new()), IpAddr::V4(Ipv4Addr::LOCALHOST))
+                .with_speed_dials(Arc::new(speed_dials))
+                .with_lnp(Arc::new(lnp))
+                .with_header_rules(Arc::new(HeaderRulesEngine::new(header_rules).unwrap()));
+        let simulator = invite_handler.routing_simulator();
+        assert_eq!(
+            simulator.steps(),
+            vec!["speed_dial", "registrar", "lnp", "header_rules"]
+        );
+
+        // 3am on a Sunday; nothing is placed, so the call is not counted
+        let at = Utc.with_ymd_and_hms(2026, 10, 18, 3, 0, 0).unwrap();
+        let trace = simulator
+            .simulate(RoutedCall::new(
+                "sip:2001@acme.example.com".to_string(),
+                "sip:*1@acme.example.com".to_string(),
+                at,
+            ))
+            .await;
+        assert_eq!(
+            trace.steps,
+            vec![
+                TraceStep {
+                    step: "speed_dial".to_string(),
+                    rule: "company speed dial *1 to 4155551234".to_string(),
+                    transformation: Some(
+                        "sip:*1@acme.example.com -> sip:+14155551234@acme.example.com".to_string()
+                    ),
+                    trunk: None,
+                    outcome: None,
+                },
+                TraceStep {
+                    step: "lnp".to_string(),
+                    rule: "+14155551234 is ported".to_string(),
+                    transformation: Some(
+                        "sip:+14155551234@acme.example.com -> \
+                         sip:+14155551234;rn=+14155550000;npdi@acme.example.com"
+                            .to_string()
+                    ),
+                    trunk: Some("carrier".to_string()),
+                    outcome: None,
+                },
+                TraceStep {
+                    step: "header_rules".to_string(),
+                    rule: "route north-america (+1*): add X-Route".to_string(),
+                    transformation: None,
+                    trunk: None,
+                    outcome: None,
+                },
+            ]
+        );
+        assert_eq!(
+            trace.outcome,
+            RouteOutcome::Forward {
+                target: "sip:+14155551234;rn=+14155550000;npdi@acme.example.com".to_string(),
+                trunk: Some("carrier".to_string()),
+            }
+        );
+        assert!(invite_handler.active_calls.read().await.is_empty());
+
+        // The live path rewrites the code the same way
+        let (target, dialed) = invite_handler
+            .resolve_speed_dial("sip:2001@acme.example.com", "sip:*1@acme.example.com")
+            .await;
+        assert_eq!(target, "sip:+14155551234@acme.example.com");
+        assert_eq!(dialed.as_deref(), Some("*1"));
+    }
 }
@@ -2,18 +2,47 @@
 //!
 //! Handles call routing and forwarding logic
 
+use super::address::uri_socket_addr;
 use super::builder::ResponseBuilder;
-use super::call_state::{CallEvent, CallState, CallStateMachine};
-use super::hold_manager::HoldManager;
+use super::call_state::{CallEvent, CallLeg, CallState, CallStateMachine};
+use super::dialog::{DialogManager, LocalReinviteOutcome, ReinviteSender};
+use super::header_rules::{HeaderContext, HeaderRulesEngine};
+use super::hold_manager::{HoldManager, HoldState};
+use super::hops::HopTracker;
 use super::message::{SipError, SipRequest, SipResponse};
+use super::overload::OverloadMonitor;
+use super::redirect::{uri_host, InviteForwarder, RedirectFollower, RedirectPolicy, TrustZone};
 use super::registrar::Registrar;
+use super::replaces::{ReplacedLeg, Replaces, TakeoverError, TakeoverPolicy};
+use super::resolver::{FailoverForwarder, SipResolver, SipTarget};
+use super::trunk_manager::{with_host, ChannelDirection, TrunkFull, TrunkManager};
+use super::transfer::{
+    transfer_invite, PendingTransfer, ReferNotify, TransferNotifier, TransferOutcome,
+    TransferPolicy,
+};
+use crate::application::call::CallApplicationService;
+use crate::domain::call::EndReason;
+use crate::domain::call_announcer::AnnouncementRequest;
+use crate::domain::call_recording::{CallRecordingManager, RecordingDirection};
+use crate::domain::call_screening::{ScreeningChoice, ScreeningFlow, ScreeningService};
 use crate::domain::cdr::{CallDetailRecord, CallDirection, CallStatus, CdrRepository};
-use crate::infrastructure::media::{MediaBridge, MediaStream, MohPlayer};
+use crate::domain::fraud_detection::FraudDetector;
+use crate::domain::shared::value_objects::SipUri;
+use crate::domain::tenant_branding::{realm_of, BrandingRegistry};
+use crate::infrastructure::call_debug::CallDebugRegistry;
+use crate::infrastructure::lnp::{with_routing_number, LnpResolver};
+use crate::infrastructure::sequence_vault::SequenceVault;
+use crate::infrastructure::setup_latency::{SetupLatencyMonitor, SetupMilestone, SetupTimeline};
+use crate::infrastructure::media::{
+    BridgeLeg, MediaBridge, MediaStream, MohClassRegistry, MohConfig, MohNowPlaying, MohPlayer, PacketFormat, Ringback,
+    RingbackConfig, RingbackPlayer, RingbackSource, DEFAULT_PTIME_MS,
+};
+use rsip::Header;
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
 use std::net::SocketAddr;
 use std::sync::Arc;
-use tokio::sync::RwLock;
+use tokio::sync::{mpsc, watch, RwLock};
 use tracing::{debug, error, info, warn};
 use uuid::Uuid;
 
@@ -28,15 +57,91 @@ pub struct ActiveCallInfo {
     pub caller_contact: Option<String>,
     pub callee_contact: Option<String>,
     pub on_hold: bool,
+    /// Packet time the caller's media is sent with, in milliseconds
+    pub caller_ptime_ms: Option<u32>,
+    /// Packet time the callee's media is sent with, in milliseconds
+    pub callee_ptime_ms: Option<u32>,
+    /// Whether the caller's RTCP is multiplexed on its RTP port
+    pub caller_rtcp_mux: Option<bool>,
+    /// Whether the callee's RTCP is multiplexed on its RTP port
+    pub callee_rtcp_mux: Option<bool>,
+    /// Caller's RTP address as given in its SDP
+    pub caller_sdp_rtp: Option<String>,
+    /// Address the caller's media actually came from, which we send to
+    /// instead of the SDP one (symmetric RTP behind NAT)
+    pub caller_latched_rtp: Option<String>,
+    pub callee_sdp_rtp: Option<String>,
+    pub callee_latched_rtp: Option<String>,
+    /// Seconds from the INVITE to the first 180/183 (or the answer)
+    pub setup_secs: Option<i64>,
+    /// Seconds alerting, so far while the call is not answered
+    pub ring_secs: Option<i64>,
+    /// Seconds answered and not on hold; paused while the call is held
+    pub talk_secs: i64,
+    /// Seconds on hold, including a hold in progress
+    pub hold_secs: i64,
+    pub hold_count: u32,
+    /// Track of the MOH class playing to the call while it is held
+    pub moh: Option<MohNowPlaying>,
+}
+
+/// Signaling metadata of a call, replicated to a hot standby node
+///
+/// Media is not replicated: a restored call keeps its dialog so in-dialog
+/// requests (BYE, re-INVITE) are answered, but has no bridge until the
+/// endpoints renegotiate media.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CallCheckpoint {
+    pub call_id: String,
+    pub caller_uri: String,
+    pub callee_uri: String,
+    pub caller_contact: Option<SocketAddr>,
+    pub callee_contact: Option<SocketAddr>,
+    pub established: bool,
+    pub cdr_id: Uuid,
+    /// CSeq of our last request in the dialog; the standby continues
+    /// above it
+    #[serde(default)]
+    pub local_cseq: u32,
+}
+
+/// A call the local side keeps on hold
+#[derive(Debug, Clone)]
+pub struct HeldCall {
+    pub call_id: String,
+    /// Party that put the call on hold
+    pub holder_uri: String,
+    /// Party waiting on hold
+    pub held_uri: String,
+    pub held_since: std::time::Instant,
+}
+
+/// Media stream of one leg of an established call
+#[derive(Clone)]
+pub struct LegMedia {
+    pub call_id: String,
+    pub leg: CallLeg,
+    pub stream: Arc<MediaStream>,
 }
 
 /// Call Leg Information
 pub struct CallLegInfo {
-    pub uri: String,
+    pub uri: SipUri,
     pub contact: Option<SocketAddr>,
     pub media_stream: Option<Arc<MediaStream>>,
 }
 
+impl CallLegInfo {
+    /// Point the leg at another party; a URI that does not parse leaves it
+    /// where it was
+    fn retarget(&mut self, uri: &str) {
+        match SipUri::parse(uri) {
+            Ok(uri) => self.uri = uri,
+            Err(e) => warn!("Leg of {} not moved to {}: {}", self.uri, uri, e),
+        }
+    }
+}
+
 /// Bridged Call
 ///
 /// Represents a call with two legs (caller and callee)
@@ -47,10 +152,36 @@ pub struct BridgedCall {
     pub state_machine: CallStateMachine,
     pub media_bridge: Option<Arc<MediaBridge>>,
     pub cdr_id: Uuid,
+    /// Blind transfer waiting for its target, with where to recover to
+    pub pending_transfer: Option<PendingTransfer>,
+    /// Party that put the call on hold; the callee unless told otherwise
+    pub holder: Option<CallLeg>,
+    /// Screening in progress; the legs are not bridged until it accepts
+    pub screening: Option<ScreeningFlow>,
+    /// From tag of the caller's INVITE
+    pub caller_tag: Option<String>,
+    /// To tag we answered the caller's INVITE with
+    pub local_tag: Option<String>,
+    /// What the caller hears while the callee leg alerts
+    pub ringback: Option<Ringback>,
+    /// Consultation call of a warm transfer, while this call waits on hold
+    pub consultation: Option<String>,
+    /// Transferor a warm transfer replaced; their BYE leaves the call up
+    pub spliced_out: Option<SipUri>,
+    /// MOH class of the queue the call waits in
+    pub moh_class: Option<String>,
+    /// A post-call survey follows; the callee's BYE leaves the caller up
+    pub survey: bool,
+    /// Setup milestones of a call set up from an INVITE
+    pub setup: Option<SetupTimeline>,
+    /// Trunk the call came in from or went out through
+    pub trunk: Option<String>,
+    /// Publishes each state the call enters
+    state_tx: watch::Sender<CallState>,
 }
 
 impl BridgedCall {
-    pub fn new(call_id: String, caller_uri: String, callee_uri: String, cdr_id: Uuid) -> Self {
+    pub fn new(call_id: String, caller_uri: SipUri, callee_uri: SipUri, cdr_id: Uuid) -> Self {
         Self {
             call_id,
             caller: CallLegInfo {
@@ -66,18 +197,89 @@ impl BridgedCall {
             state_machine: CallStateMachine::new(),
             media_bridge: None,
             cdr_id,
+            pending_transfer: None,
+            holder: None,
+            screening: None,
+            caller_tag: None,
+            local_tag: None,
+            ringback: None,
+            consultation: None,
+            spliced_out: None,
+            moh_class: None,
+            survey: false,
+            setup: None,
+            trunk: None,
+            state_tx: watch::channel(CallState::Trying).0,
         }
     }
 
+    /// Id of the call's `Call` aggregate, whose saved state is the call's
+    /// lifecycle; the same as the CDR id
+    pub fn aggregate_id(&self) -> crate::domain::shared::value_objects::CallId {
+        crate::domain::shared::value_objects::CallId::from_uuid(self.cdr_id)
+    }
+
     pub fn state(&self) -> &CallState {
         self.state_machine.state()
     }
 
     pub fn process_event(&mut self, event: CallEvent) -> Result<(), String> {
-        self.state_machine.process_event(event)
+        self.state_machine.process_event(event)?;
+        self.state_tx.send_replace(self.state_machine.state().clone());
+        Ok(())
+    }
+
+    pub fn leg(&self, leg: &CallLeg) -> &CallLegInfo {
+        match leg {
+            CallLeg::Caller => &self.caller,
+            CallLeg::Callee => &self.callee,
+        }
+    }
+
+    pub fn leg_mut(&mut self, leg: &CallLeg) -> &mut CallLegInfo {
+        match leg {
+            CallLeg::Caller => &mut self.caller,
+            CallLeg::Callee => &mut self.callee,
+        }
     }
 }
 
+/// Result of a screened user's choice
+#[derive(Debug, Clone)]
+pub enum ScreeningOutcome {
+    /// The legs are bridged
+    Accepted,
+    /// The callee leg now goes to voicemail, with the caller's recorded name
+    Voicemail {
+        target: String,
+        name_recording: Option<String>,
+    },
+    /// The call is rejected; `prompt` is played to the caller before hangup
+    Rejected { prompt: AnnouncementRequest },
+}
+
+/// An INVITE/Replaces asking to take over a call
+#[derive(Debug, Clone)]
+pub struct TakeoverRequest {
+    pub replaces: Replaces,
+    /// Call-ID of the taking-over INVITE
+    pub call_id: String,
+    pub requester_uri: String,
+    /// Authenticated user of the taking-over device
+    pub requester_user: String,
+    pub contact: Option<SocketAddr>,
+}
+
+/// A takeover that went through
+pub struct Takeover {
+    /// The call taken over, as known to the router
+    pub call_id: String,
+    /// The device to release
+    pub replaced: ReplacedLeg,
+    /// Bridge now carrying the call's media, when media was given
+    pub bridge: Option<Arc<MediaBridge>>,
+}
+
 /// Call Router
 ///
 /// Routes calls between caller and callee
@@ -86,7 +288,47 @@ pub struct CallRouter {
     active_calls: Arc<RwLock<HashMap<String, BridgedCall>>>,
     cdr_repository: Option<Arc<dyn CdrRepository>>,
     hold_manager: Arc<HoldManager>,
+    dialog_manager: Arc<DialogManager>,
+    reinvite_sender: Option<Arc<dyn ReinviteSender>>,
     moh_players: Arc<RwLock<HashMap<String, Arc<MohPlayer>>>>,
+    fraud_detector: Option<Arc<FraudDetector>>,
+    redirect_policy: RedirectPolicy,
+    transfer_policy: TransferPolicy,
+    call_events: Option<Arc<CallApplicationService>>,
+    /// Via branches and loop detection of forwarded INVITEs
+    hops: Option<Arc<HopTracker>>,
+    /// Tenant MOH and outbound caller identity
+    branding: Option<Arc<BrandingRegistry>>,
+    /// MOH classes selected per queue or tenant
+    moh_classes: Option<Arc<MohClassRegistry>>,
+    /// Per-trunk and per-route header rules of forwarded INVITEs
+    header_rules: Option<Arc<HeaderRulesEngine>>,
+    /// Calls and users with per-call debug logging enabled
+    call_debug: Option<Arc<CallDebugRegistry>>,
+    /// Pre-answer screening of calls to users who enabled it
+    screening: Option<Arc<ScreeningService>>,
+    /// Records answered calls when auto-record is on
+    recording: Option<Arc<CallRecordingManager>>,
+    /// Routing overrides of ported numbers
+    lnp: Option<Arc<LnpResolver>>,
+    /// Skips optional work (portability lookups, call debugging) under
+    /// overload
+    overload: Option<Arc<OverloadMonitor>>,
+    /// NAPTR/SRV resolution and failover of forwarded INVITEs
+    sip_resolver: Option<Arc<SipResolver>>,
+    /// Setup times of calls, checked against latency SLOs
+    setup_latency: Option<Arc<SetupLatencyMonitor>>,
+    /// Channels of trunks, taken by calls on them
+    trunks: Option<Arc<TrunkManager>>,
+    /// Our CSeqs and RTP SSRCs, kept across restarts
+    sequences: Option<Arc<SequenceVault>>,
+    /// Who may take over whose calls with INVITE/Replaces
+    takeover_policy: TakeoverPolicy,
+    /// Call-IDs of taking-over INVITEs, mapped to the call they took over
+    aliases: Arc<RwLock<HashMap<String, String>>>,
+    /// Ringback policy and tone plans, per trunk and tenant
+    ringback: RingbackConfig,
+    ringback_players: Arc<RwLock<HashMap<String, Arc<RingbackPlayer>>>>,
 }
 
 impl CallRouter {
@@ -96,7 +338,30 @@ impl CallRouter {
             active_calls: Arc::new(RwLock::new(HashMap::new())),
             cdr_repository: None,
             hold_manager: Arc::new(HoldManager::new()),
+            dialog_manager: Arc::new(DialogManager::new()),
+            reinvite_sender: None,
             moh_players: Arc::new(RwLock::new(HashMap::new())),
+            fraud_detector: None,
+            redirect_policy: RedirectPolicy::default(),
+            transfer_policy: TransferPolicy::default(),
+            call_events: None,
+            hops: None,
+            branding: None,
+            moh_classes: None,
+            header_rules: None,
+            call_debug: None,
+            screening: None,
+            recording: None,
+            lnp: None,
+            overload: None,
+            sip_resolver: None,
+            setup_latency: None,
+            trunks: None,
+            sequences: None,
+            takeover_policy: TakeoverPolicy::default(),
+            aliases: Arc::new(RwLock::new(HashMap::new())),
+            ringback: RingbackConfig::default(),
+            ringback_players: Arc::new(RwLock::new(HashMap::new())),
         }
     }
 
@@ -105,15 +370,226 @@ impl CallRouter {
         self
     }
 
+    /// Send hold and resume re-INVITEs through `sender`
+    pub fn with_reinvite_sender(mut self, sender: Arc<dyn ReinviteSender>) -> Self {
+        self.reinvite_sender = Some(sender);
+        self
+    }
+
+    /// Report ended calls to the fraud detector (minutes, concurrent calls)
+    pub fn with_fraud_detector(mut self, fraud_detector: Arc<FraudDetector>) -> Self {
+        self.fraud_detector = Some(fraud_detector);
+        self
+    }
+
+    /// Limits on following 3xx redirects of forwarded calls
+    pub fn with_redirect_policy(mut self, redirect_policy: RedirectPolicy) -> Self {
+        self.redirect_policy = redirect_policy;
+        self
+    }
+
+    /// Timeout and recovery destination of blind transfers
+    pub fn with_transfer_policy(mut self, transfer_policy: TransferPolicy) -> Self {
+        self.transfer_policy = transfer_policy;
+        self
+    }
+
+    pub fn transfer_policy(&self) -> &TransferPolicy {
+        &self.transfer_policy
+    }
+
+    /// Publish call lifecycle events; answers and hangups reach the CDR
+    /// through the event bus
+    pub fn with_call_events(mut self, call_events: Arc<CallApplicationService>) -> Self {
+        self.call_events = Some(call_events);
+        self
+    }
+
+    /// Forward INVITEs with our own Via and branch
+    pub fn with_hop_tracker(mut self, hops: Arc<HopTracker>) -> Self {
+        self.hops = Some(hops);
+        self
+    }
+
+    /// Play tenant MOH on hold and brand INVITEs leaving toward trunks
+    pub fn with_branding(mut self, branding: Arc<BrandingRegistry>) -> Self {
+        self.branding = Some(branding);
+        self
+    }
+
+    /// Play the MOH class of a call's queue or tenant on hold
+    pub fn with_moh_classes(mut self, moh_classes: Arc<MohClassRegistry>) -> Self {
+        self.moh_classes = Some(moh_classes);
+        self
+    }
+
+    /// Add, remove and pass through headers of forwarded INVITEs per
+    /// trunk and route
+    pub fn with_header_rules(mut self, header_rules: Arc<HeaderRulesEngine>) -> Self {
+        self.header_rules = Some(header_rules);
+        self
+    }
+
+    /// Debug the calls of pre-armed users and disarm calls as they end
+    pub fn with_call_debug(mut self, call_debug: Arc<CallDebugRegistry>) -> Self {
+        self.call_debug = Some(call_debug);
+        self
+    }
+
+    /// Screen calls to users who enabled it before bridging them
+    pub fn with_call_screening(mut self, screening: Arc<ScreeningService>) -> Self {
+        self.screening = Some(screening);
+        self
+    }
+
+    /// Record answered calls when the manager auto-records
+    pub fn with_call_recording(mut self, recording: Arc<CallRecordingManager>) -> Self {
+        self.recording = Some(recording);
+        self
+    }
+
+    /// Route ported numbers by the routing number or trunk their lookup
+    /// returns
+    pub fn with_lnp(mut self, lnp: Arc<LnpResolver>) -> Self {
+        self.lnp = Some(lnp);
+        self
+    }
+
+    /// Keep our dialog CSeqs and the SSRCs of call media in `vault`, so
+    /// they never go backwards after a restart or takeover
+    pub fn with_sequence_vault(mut self, vault: Arc<SequenceVault>) -> Self {
+        self.dialog_manager = Arc::new(DialogManager::new().with_sequence_vault(vault.clone()));
+        self.sequences = Some(vault);
+        self
+    }
+
+    /// Sequence vault of this router's dialogs, when one is set
+    pub fn sequence_vault(&self) -> Option<Arc<SequenceVault>> {
+        self.sequences.clone()
+    }
+
+    /// Skip optional work of new calls while `overload` is shedding
+    pub fn with_overload_monitor(mut self, overload: Arc<OverloadMonitor>) -> Self {
+        self.overload = Some(overload);
+        self
+    }
+
+    /// Whether optional work is being shed
+    fn shedding(&self) -> bool {
+        self.overload.as_ref().is_some_and(|o| o.is_shedding())
+    }
+
+    /// Send forwarded INVITEs to the NAPTR/SRV targets of their destination,
+    /// moving on to the next target when one is unreachable
+    pub fn with_sip_resolver(mut self, resolver: Arc<SipResolver>) -> Self {
+        self.sip_resolver = Some(resolver);
+        self
+    }
+
+    /// File the setup times of calls with `monitor`
+    pub fn with_setup_latency(mut self, monitor: Arc<SetupLatencyMonitor>) -> Self {
+        self.setup_latency = Some(monitor);
+        self
+    }
+
+    /// Count trunk channels with `trunks`, refusing calls on full trunks
+    pub fn with_trunk_manager(mut self, trunks: Arc<TrunkManager>) -> Self {
+        self.trunks = Some(trunks);
+        self
+    }
+
+    /// Trunk channel accounting, when enabled
+    pub fn trunk_manager(&self) -> Option<Arc<TrunkManager>> {
+        self.trunks.clone()
+    }
+
+    /// Who may take over whose calls with INVITE/Replaces
+    pub fn with_takeover_policy(mut self, takeover_policy: TakeoverPolicy) -> Self {
+        self.takeover_policy = takeover_policy;
+        self
+    }
+
+    /// Play local ringback or relay early media while callees alert
+    pub fn with_ringback(mut self, ringback: RingbackConfig) -> Self {
+        self.ringback = ringback;
+        self
+    }
+
+    /// End a call's aggregate
+    async fn record_call_ended(&self, call_id: &str, reason: EndReason) {
+        if let Some(events) = &self.call_events {
+            if let Err(e) = events.end(call_id, reason).await {
+                warn!("Failed to record end of call {}: {}", call_id, e);
+            }
+        }
+    }
+
+    /// Release the caller's fraud counters for a finished call
+    fn report_call_ended(&self, call_id: &str, call: &BridgedCall) {
+        if let Some(fraud) = &self.fraud_detector {
+            let caller = Self::leg_user(&call.caller.uri);
+            let duration = call.state_machine.stats().call_duration().unwrap_or_default();
+            fraud.call_ended(&caller, call_id, duration);
+        }
+        // Answered calls were filed when answered
+        if call.setup.is_some_and(|setup| setup.answered.is_none()) {
+            self.report_setup(call);
+        }
+    }
+
+    /// File the setup times of a call whose setup is over under its tenant
+    /// and trunk
+    fn report_setup(&self, call: &BridgedCall) {
+        if let (Some(monitor), Some(setup)) = (&self.setup_latency, &call.setup) {
+            let caller = call.caller.uri.to_string();
+            let tenant = match &self.branding {
+                Some(branding) => branding.tenant_of(&caller, &call.callee.uri.to_string()),
+                None => realm_of(&caller).map(str::to_string),
+            };
+            monitor.record(setup, tenant.as_deref(), call.trunk.as_deref());
+        }
+    }
+
+    /// Record on the CDR of a call when it was provisionally answered and
+    /// answered, from its INVITE
+    async fn record_setup_latency(&self, call_id: &str, cdr_id: Uuid, setup: &SetupTimeline) {
+        if let Some(cdr_repo) = &self.cdr_repository {
+            if let Ok(Some(mut cdr)) = cdr_repo.get_by_id(cdr_id).await {
+                cdr.set_setup_latency(
+                    setup.millis(SetupMilestone::Authenticated),
+                    setup.millis(SetupMilestone::Routed),
+                    setup.millis(SetupMilestone::FirstProvisional),
+                    setup.millis(SetupMilestone::Answered),
+                );
+                if let Err(e) = cdr_repo.update(&cdr).await {
+                    error!("Failed to record setup latency of call {}: {}", call_id, e);
+                }
+            }
+        }
+    }
+
     /// Extract username from SIP URI
-    /// Example: "sip:alice@example.com" -> "alice"
-    fn extract_username(uri: &str) -> String {
-        uri.trim_start_matches("sip:")
-            .trim_start_matches("sips:")
-            .split('@')
-            .next()
-            .unwrap_or("unknown")
-            .to_string()
+    /// Example: "sip:alice@example.com;transport=tcp" -> "alice"
+    pub(crate) fn extract_username(uri: &str) -> String {
+        match SipUri::parse(uri) {
+            Ok(uri) => Self::leg_user(&uri),
+            Err(_) => uri
+                .trim_start_matches("sip:")
+                .trim_start_matches("sips:")
+                .split('@')
+                .next()
+                .unwrap_or("unknown")
+                .to_string(),
+        }
+    }
+
+    /// User part of a leg's URI, or its host when it has none
+    fn leg_user(uri: &SipUri) -> String {
+        if uri.user().is_empty() {
+            uri.host().to_string()
+        } else {
+            uri.user().to_string()
+        }
     }
 
     /// Extract IP from SocketAddr
@@ -128,13 +604,53 @@ impl CallRouter {
         caller_uri: String,
         callee_uri: String,
     ) -> Result<(), String> {
+        self.create_dialed_call(call_id, caller_uri, callee_uri, None)
+            .await
+    }
+
+    /// Create a call whose destination was rewritten from `dialed`
+    /// (e.g. a speed dial code); the CDR records both
+    pub async fn create_dialed_call(
+        &self,
+        call_id: String,
+        caller_uri: String,
+        callee_uri: String,
+        dialed: Option<String>,
+    ) -> Result<(), String> {
+        self.create_call_with_setup(call_id, caller_uri, callee_uri, dialed, None)
+            .await
+    }
+
+    /// Create a call set up from an INVITE, whose `setup` so far (received,
+    /// authenticated, routed) goes in its CDR
+    pub async fn create_call_with_setup(
+        &self,
+        call_id: String,
+        caller_uri: String,
+        callee_uri: String,
+        dialed: Option<String>,
+        setup: Option<SetupTimeline>,
+    ) -> Result<(), String> {
+        let caller = SipUri::parse(&caller_uri)
+            .map_err(|e| format!("Invalid caller URI '{}': {}", caller_uri, e))?;
+        let callee = SipUri::parse(&callee_uri)
+            .map_err(|e| format!("Invalid callee URI '{}': {}", callee_uri, e))?;
+
+        // Calls of pre-armed users are debugged from their first event,
+        // unless optional work is shed
+        if let Some(call_debug) = self.call_debug.as_ref().filter(|_| !self.shedding()) {
+            let caller = Self::leg_user(&caller);
+            let callee = Self::leg_user(&callee);
+            call_debug.call_started(&call_id, &[&caller, &callee]);
+        }
+
         // Create CDR if repository is available
         let cdr_id = if let Some(ref cdr_repo) = self.cdr_repository {
-            let caller_username = Self::extract_username(&caller_uri);
-            let callee_username = Self::extract_username(&callee_uri);
+            let caller_username = Self::leg_user(&caller);
+            let callee_username = Self::leg_user(&callee);
 
             // Create initial CDR (we don't have IPs yet at this point)
-            let cdr = CallDetailRecord::new(
+            let mut cdr = CallDetailRecord::new(
                 call_id.clone(),
                 caller_username,
                 caller_uri.clone(),
@@ -143,6 +659,17 @@ impl CallRouter {
                 callee_uri.clone(),
                 CallDirection::Outbound, // Default, should be determined by context
             );
+            if let Some(dialed) = dialed {
+                cdr.set_dialed_number(dialed);
+            }
+            if let Some(setup) = &setup {
+                cdr.set_setup_latency(
+                    setup.millis(SetupMilestone::Authenticated),
+                    setup.millis(SetupMilestone::Routed),
+                    None,
+                    None,
+                );
+            }
 
             let cdr_id = cdr.id;
 
@@ -158,7 +685,19 @@ impl CallRouter {
             Uuid::new_v4()
         };
 
-        let call = BridgedCall::new(call_id.clone(), caller_uri, callee_uri, cdr_id);
+        // The call's aggregate shares the CDR id
+        if let Some(events) = &self.call_events {
+            let direction = crate::domain::call::CallDirection::Outbound;
+            if let Err(e) = events
+                .start(&call_id, cdr_id, &caller_uri, &callee_uri, direction)
+                .await
+            {
+                warn!("Failed to record start of call {}: {}", call_id, e);
+            }
+        }
+
+        let mut call = BridgedCall::new(call_id.clone(), caller, callee, cdr_id);
+        call.setup = setup;
 
         let mut calls = self.active_calls.write().await;
         calls.insert(call_id, call);
@@ -166,13 +705,220 @@ impl CallRouter {
         Ok(())
     }
 
+    /// Place a call from `caller_uri` to `callee_uri` on the caller's
+    /// behalf (e.g. dialed from an agent desktop); returns its Call-ID
+    ///
+    /// The call rings until it is answered or hung up.
+    pub async fn originate(&self, caller_uri: &str, callee_uri: &str) -> Result<String, String> {
+        let call_id = Uuid::new_v4().to_string();
+        self.create_call(call_id.clone(), caller_uri.to_string(), callee_uri.to_string())
+            .await?;
+
+        if let Some(call) = self.active_calls.write().await.get_mut(&call_id) {
+            call.process_event(CallEvent::Ringing)?;
+        }
+        if let Some(events) = &self.call_events {
+            if let Err(e) = events.ring(&call_id).await {
+                warn!("Failed to record ringing of call {}: {}", call_id, e);
+            }
+        }
+
+        info!("Call {} originated from {} to {}", call_id, caller_uri, callee_uri);
+        Ok(call_id)
+    }
+
+    /// Forward a call's INVITE to `target`, following 3xx redirects
+    ///
+    /// Every target that redirected or failed is recorded as its own CDR leg
+    /// sharing the call's correlation id; the call's CDR gets the target
+    /// finally reached and the number of redirects followed. Returns the
+    /// response for the caller (482 when the redirects loop, 483 when
+    /// Max-Forwards is used up).
+    pub async fn forward_with_redirects(
+        &self,
+        call_id: &str,
+        request: &SipRequest,
+        target: &str,
+        origin: TrustZone,
+        forwarder: &dyn InviteForwarder,
+    ) -> Result<SipResponse, SipError> {
+        let mut follower = RedirectFollower::new(self.redirect_policy.clone());
+        if let Some(hops) = &self.hops {
+            follower = follower.with_hop_tracker(hops.clone());
+        }
+        if let Some(branding) = &self.branding {
+            let caller_uri = self
+                .active_calls
+                .read()
+                .await
+                .get(call_id)
+                .map(|call| call.caller.uri.to_string());
+            if let Some(tenant) = caller_uri
+                .as_deref()
+                .and_then(realm_of)
+                .and_then(|realm| branding.get(realm))
+            {
+                follower = follower.with_branding(tenant);
+            }
+        }
+        // Applied before the call is routed; no answer within the budget
+        // (or shedding under overload) routes it as dialed
+        let mut target = target.to_string();
+        let mut trunk = None;
+        if let Some(lnp) = self.lnp.as_ref().filter(|_| !self.shedding()) {
+            let context = HeaderContext::from_request(request);
+            if let Some(dialed) = context.callee.as_deref() {
+                if let Some(routing) = lnp.resolve(dialed, context.tenant.as_deref()).await {
+                    info!(
+                        "Call {}: {} is ported (rn {:?}, trunk {:?})",
+                        call_id, dialed, routing.routing_number, routing.trunk
+                    );
+                    if let Some(routing_number) = &routing.routing_number {
+                        target = with_routing_number(&target, routing_number);
+                    }
+                    trunk = routing.trunk;
+                }
+            }
+        }
+        // An outbound call on a trunk takes one of its channels, or one of
+        // the next failover trunk's
+        if let Some(trunks) = &self.trunks {
+            let routed = trunk.clone().or_else(|| {
+                self.header_rules
+                    .as_ref()?
+                    .trunk_of(uri_host(&target))
+                    .map(str::to_string)
+            });
+            if let Some(routed) = routed {
+                match trunks.acquire_outbound(call_id, &routed) {
+                    Ok(taken) => {
+                        if taken != routed {
+                            if let Some(host) = trunks.host_of(&taken) {
+                                target = with_host(&target, host);
+                            }
+                        }
+                        trunk = Some(taken);
+                    }
+                    Err(e) => {
+                        warn!("Call {} rejected: {}", call_id, e);
+                        return ResponseBuilder::new(503)
+                            .header(Header::Other("Retry-After".to_string(), "30".to_string()))
+                            .build_for_request(request);
+                    }
+                }
+            }
+        }
+        if let Some(header_rules) = &self.header_rules {
+            let mut context = HeaderContext::from_request(request);
+            if let Some(call) = self.active_calls.read().await.get(call_id) {
+                context = context.with_cdr_id(call.cdr_id);
+            }
+            context.trunk = trunk.clone();
+            follower = follower.with_header_rules(header_rules.clone(), context);
+        }
+        let failover = self
+            .sip_resolver
+            .as_deref()
+            .map(|resolver| FailoverForwarder::new(forwarder, resolver));
+        let outcome = match &failover {
+            Some(failover) => follower.forward(failover, origin, &target, request).await?,
+            None => follower.forward(forwarder, origin, &target, request).await?,
+        };
+        if let Some(served_by) = failover.and_then(|failover| failover.served_by()) {
+            self.set_served_by(call_id, &served_by).await;
+        }
+
+        // The trunk billing supervises the call by
+        let trunk = trunk.or_else(|| {
+            self.header_rules
+                .as_ref()?
+                .trunk_of(uri_host(&outcome.target))
+                .map(str::to_string)
+        });
+        if let Some(trunk) = trunk {
+            self.set_trunk(call_id, &trunk).await;
+        }
+
+        let cdr_id = {
+            let mut calls = self.active_calls.write().await;
+            match calls.get_mut(call_id) {
+                Some(call) => {
+                    if outcome.redirect_count > 0 {
+                        call.callee.retarget(&outcome.target);
+                    }
+                    Some(call.cdr_id)
+                }
+                None => None,
+            }
+        };
+
+        if let (Some(cdr_repo), Some(cdr_id)) = (&self.cdr_repository, cdr_id) {
+            if outcome.hops.len() > 1 {
+                if let Ok(Some(mut cdr)) = cdr_repo.get_by_id(cdr_id).await {
+                    let correlation_id = cdr
+                        .correlation_id
+                        .clone()
+                        .unwrap_or_else(|| call_id.to_string());
+
+                    // The last hop is the call's own CDR
+                    for hop in &outcome.hops[..outcome.hops.len() - 1] {
+                        let mut leg = CallDetailRecord::new(
+                            call_id.to_string(),
+                            cdr.caller_username.clone(),
+                            cdr.caller_uri.clone(),
+                            cdr.caller_ip.clone(),
+                            Self::extract_username(&hop.target),
+                            hop.target.clone(),
+                            cdr.direction,
+                        );
+                        leg.set_correlation_id(correlation_id.clone());
+                        let reason = match hop.status {
+                            Some(status) if (300..400).contains(&status) => {
+                                format!("Redirected to {}", hop.redirected_to.join(", "))
+                            }
+                            Some(_) => "Target rejected call".to_string(),
+                            None => "Target unreachable".to_string(),
+                        };
+                        leg.mark_ended(CallStatus::Failed, Some(reason), hop.status);
+                        if let Err(e) = cdr_repo.create(&leg).await {
+                            error!("Failed to create redirect CDR leg for {}: {}", call_id, e);
+                        }
+                    }
+
+                    cdr.set_correlation_id(correlation_id);
+                    cdr.set_redirected(outcome.target.clone(), outcome.redirect_count as i32);
+                    if let Err(e) = cdr_repo.update(&cdr).await {
+                        error!("Failed to update CDR after redirects: {}", e);
+                    }
+                }
+            }
+        }
+
+        if outcome.loop_detected {
+            warn!("Call {} aborted: redirect loop", call_id);
+        } else if outcome.redirect_count > 0 {
+            info!(
+                "Call {} reached {} after {} redirect(s)",
+                call_id, outcome.target, outcome.redirect_count
+            );
+        }
+
+        Ok(outcome.response)
+    }
+
     /// Find callee contact
     pub async fn find_callee_contact(&self, callee_uri: &str) -> Option<SocketAddr> {
+        let callee_uri = SipUri::parse(callee_uri).ok()?;
+        self.find_contact_of(&callee_uri).await
+    }
+
+    /// Contact of the first binding registered for a URI's AoR
+    pub async fn find_contact_of(&self, callee_uri: &SipUri) -> Option<SocketAddr> {
         // Look up callee in registrar
-        if let Some(bindings) = self.registrar.get_bindings(callee_uri).await {
+        if let Some(bindings) = self.registrar.bindings_for_uri(callee_uri).await {
             if let Some(binding) = bindings.first() {
-                // Parse contact string to SocketAddr
-                if let Ok(addr) = binding.contact.parse::<SocketAddr>() {
+                // Contact URI host, e.g. sip:bob@[2001:db8::1]:5060
+                if let Some(addr) = uri_socket_addr(&binding.contact) {
                     return Some(addr);
                 }
             }
@@ -204,14 +950,26 @@ impl CallRouter {
         request: &SipRequest,
     ) -> Result<SipResponse, SipError> {
         // Update call state
+        let mut local_tag = None;
         if let Some(call) = self.active_calls.write().await.get_mut(call_id) {
+            local_tag = call.local_tag.clone();
+            if let Some(setup) = call.setup.as_mut() {
+                setup.mark(SetupMilestone::FirstProvisional, std::time::Instant::now());
+            }
             if let Err(e) = call.process_event(CallEvent::Ringing) {
                 warn!("State transition error: {}", e);
+            } else if let Some(events) = &self.call_events {
+                if let Err(e) = events.ring(call_id).await {
+                    warn!("Failed to record ringing of call {}: {}", call_id, e);
+                }
             }
         }
 
-        ResponseBuilder::new(180)
-            .build_for_request(request)
+        let mut response = ResponseBuilder::new(180);
+        if let Some(tag) = &local_tag {
+            response = response.to_tag(tag);
+        }
+        response.build_for_request(request)
     }
 
     /// Generate provisional response (183 Session Progress)
@@ -222,6 +980,9 @@ impl CallRouter {
     ) -> Result<SipResponse, SipError> {
         // Update call state
         if let Some(call) = self.active_calls.write().await.get_mut(call_id) {
+            if let Some(setup) = call.setup.as_mut() {
+                setup.mark(SetupMilestone::FirstProvisional, std::time::Instant::now());
+            }
             if let Err(e) = call.process_event(CallEvent::SessionProgress) {
                 warn!("State transition error: {}", e);
             }
@@ -236,15 +997,32 @@ impl CallRouter {
         let mut calls = self.active_calls.write().await;
         if let Some(call) = calls.get_mut(call_id) {
             call.process_event(CallEvent::Answer)?;
+            call.ringback = None;
             info!("Call {} answered", call_id);
+            self.stop_ringback(call_id).await;
 
-            // Update CDR with answer time
-            if let Some(ref cdr_repo) = self.cdr_repository {
-                if let Ok(Some(mut cdr)) = cdr_repo.get_by_id(call.cdr_id).await {
-                    cdr.mark_answered();
-                    if let Err(e) = cdr_repo.update(&cdr).await {
-                        error!("Failed to update CDR on answer: {}", e);
-                    }
+            if let Some(setup) = call.setup.as_mut() {
+                setup.mark(SetupMilestone::Answered, std::time::Instant::now());
+                let setup = *setup;
+                self.report_setup(call);
+                self.record_setup_latency(call_id, call.cdr_id, &setup).await;
+            }
+
+            // A refused recording (e.g. disk full) does not fail the call
+            if let Some(recording) = self.recording.as_ref().filter(|r| r.is_auto_record()) {
+                if let Err(e) = recording.start_recording(
+                    call_id.to_string(),
+                    call.caller.uri.to_string(),
+                    call.callee.uri.to_string(),
+                    RecordingDirection::Both,
+                ) {
+                    warn!("{}", e);
+                }
+            }
+
+            if let Some(events) = &self.call_events {
+                if let Err(e) = events.answer(call_id).await {
+                    warn!("Failed to record answer of call {}: {}", call_id, e);
                 }
             }
 
@@ -260,23 +1038,18 @@ impl CallRouter {
         if let Some(call) = calls.get_mut(call_id) {
             call.process_event(CallEvent::Reject)?;
             info!("Call {} rejected: {}", call_id, reason);
-
-            // Update CDR with rejection
-            if let Some(ref cdr_repo) = self.cdr_repository {
-                if let Ok(Some(mut cdr)) = cdr_repo.get_by_id(call.cdr_id).await {
-                    // Determine status based on reason
-                    let status = match reason.to_lowercase().as_str() {
-                        "busy" => CallStatus::Busy,
-                        "declined" | "not found" => CallStatus::Rejected,
-                        _ => CallStatus::Failed,
-                    };
-                    cdr.mark_ended(status, Some(reason.to_string()), None);
-                    if let Err(e) = cdr_repo.update(&cdr).await {
-                        error!("Failed to update CDR on reject: {}", e);
-                    }
-                }
+            self.report_call_ended(call_id, call);
+            if let Some(setup) = call.setup {
+                self.record_setup_latency(call_id, call.cdr_id, &setup).await;
             }
 
+            let end_reason = match reason.to_lowercase().as_str() {
+                "busy" => EndReason::Busy,
+                "declined" | "not found" => EndReason::Rejected,
+                _ => EndReason::Failed(reason.to_string()),
+            };
+            self.record_call_ended(call_id, end_reason).await;
+
             Ok(())
         } else {
             Err(format!("Call {} not found", call_id))
@@ -333,38 +1106,17 @@ impl CallRouter {
         let mut calls = self.active_calls.write().await;
         if let Some(mut call) = calls.remove(call_id) {
             call.process_event(CallEvent::Bye)?;
-
-            // Update CDR with completion
-            if let Some(ref cdr_repo) = self.cdr_repository {
-                if let Ok(Some(mut cdr)) = cdr_repo.get_by_id(call.cdr_id).await {
-                    cdr.mark_ended(CallStatus::Completed, Some("Normal clearing".to_string()), Some(200));
-
-                    // TODO: Add media stats when MediaBridge provides stats API
-                    // For now, we'll just mark the call as completed without media stats
-
-                    if let Err(e) = cdr_repo.update(&cdr).await {
-                        error!("Failed to update CDR on termination: {}", e);
-                    }
-                }
-            }
-
-            // Stop media
-            if let Some(bridge) = call.media_bridge {
-                bridge.stop().await;
-                debug!("Media bridge stopped for call {}", call_id);
-            }
-
-            // Stop and cleanup MOH if playing
-            {
-                let mut moh_players = self.moh_players.write().await;
-                if let Some(moh_player) = moh_players.remove(call_id) {
-                    let _ = moh_player.stop().await;
-                    debug!("MOH stopped and removed for call {}", call_id);
-                }
-            }
-
-            // Clean up hold state
-            self.hold_manager.remove_call(call_id).await;
+            self.report_call_ended(call_id, &call);
+            drop(calls);
+
+            let reason = call
+                .screening
+                .as_ref()
+                .and_then(ScreeningFlow::end_reason)
+                .unwrap_or(EndReason::NormalClearing);
+            // TODO: Add media stats to the CDR when MediaBridge provides stats API
+            self.record_call_ended(call_id, reason).await;
+            self.release_call_resources(call_id, call).await;
 
             info!("Call {} terminated", call_id);
             Ok(())
@@ -373,18 +1125,118 @@ impl CallRouter {
         }
     }
 
-    /// Set media bridge for call
-    pub async fn set_media_bridge(&self, call_id: &str, bridge: Arc<MediaBridge>) {
-        let mut calls = self.active_calls.write().await;
-        if let Some(call) = calls.get_mut(call_id) {
-            call.media_bridge = Some(bridge);
-        }
-    }
+    /// End a call the PBX itself tears down, e.g. for maintenance, with
+    /// `reason` in its CDR. The BYEs to the parties are up to the caller.
+    pub async fn end_call(&self, call_id: &str, reason: EndReason) -> Result<(), String> {
+        let call = self.active_calls.write().await.remove(call_id);
+        let Some(call) = call else {
+            return Err(format!("Call {} not found", call_id));
+        };
+        self.report_call_ended(call_id, &call);
+        self.record_call_ended(call_id, reason).await;
+        self.release_call_resources(call_id, call).await;
 
-    /// Get call state
-    pub async fn get_call_state(&self, call_id: &str) -> Option<CallState> {
-        let calls = self.active_calls.read().await;
-        calls.get(call_id).map(|call| call.state().clone())
+        info!("Call {} ended by the PBX", call_id);
+        Ok(())
+    }
+
+    /// Drop a call that failed during setup, closing anything allocated
+    /// for it. A call not already ended (e.g. rejected) ends as failed.
+    pub async fn discard_call(&self, call_id: &str) -> bool {
+        let call = self.active_calls.write().await.remove(call_id);
+        match call {
+            Some(call) => {
+                if call.state().is_active() {
+                    self.report_call_ended(call_id, &call);
+                    self.record_call_ended(call_id, EndReason::Failed("Call setup failed".to_string()))
+                        .await;
+                }
+                self.release_call_resources(call_id, call).await;
+                debug!("Call {} discarded", call_id);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Close media and MOH and forget hold and dialog state of a call
+    async fn release_call_resources(&self, call_id: &str, call: BridgedCall) {
+        if let Some(trunks) = &self.trunks {
+            trunks.release(call_id);
+        }
+        if let Some(call_debug) = &self.call_debug {
+            call_debug.disarm_call(call_id);
+        }
+        if let Some(recording) = &self.recording {
+            if recording.get_active_recording(call_id).is_some() {
+                if let Err(e) = recording.stop_recording(call_id) {
+                    warn!("Failed to stop recording of call {}: {}", call_id, e);
+                }
+            }
+        }
+        if let Some(bridge) = call.media_bridge {
+            bridge.close().await;
+            debug!("Media bridge closed for call {}", call_id);
+        }
+        for stream in [call.caller.media_stream, call.callee.media_stream].into_iter().flatten() {
+            stream.close().await;
+        }
+
+        self.stop_ringback(call_id).await;
+
+        // Stop and cleanup MOH if playing
+        {
+            let moh_player = self.moh_players.write().await.remove(call_id);
+            if let Some(moh_player) = moh_player {
+                moh_player.close().await;
+                debug!("MOH closed and removed for call {}", call_id);
+            }
+        }
+
+        // Clean up hold and offer/answer state
+        self.hold_manager.remove_call(call_id).await;
+        self.dialog_manager.remove(call_id).await;
+        let aliases: Vec<String> = {
+            let mut aliases = self.aliases.write().await;
+            let ids = aliases
+                .iter()
+                .filter(|(_, target)| target.as_str() == call_id)
+                .map(|(alias, _)| alias.clone())
+                .collect::<Vec<_>>();
+            for alias in &ids {
+                aliases.remove(alias);
+            }
+            ids
+        };
+        for alias in aliases {
+            self.dialog_manager.remove(&alias).await;
+        }
+    }
+
+    /// Set media bridge for call
+    pub async fn set_media_bridge(&self, call_id: &str, bridge: Arc<MediaBridge>) {
+        let mut calls = self.active_calls.write().await;
+        if let Some(call) = calls.get_mut(call_id) {
+            call.media_bridge = Some(bridge);
+        }
+    }
+
+    /// Media bridge of a call, once its legs are bridged
+    pub async fn media_bridge(&self, call_id: &str) -> Option<Arc<MediaBridge>> {
+        self.active_calls.read().await.get(call_id)?.media_bridge.clone()
+    }
+
+    /// Get call state
+    pub async fn get_call_state(&self, call_id: &str) -> Option<CallState> {
+        let calls = self.active_calls.read().await;
+        calls.get(call_id).map(|call| call.state().clone())
+    }
+
+    /// Receiver of the states a call enters; its sender is dropped when
+    /// the call is removed
+    pub async fn watch_call_state(&self, call_id: &str) -> Option<watch::Receiver<CallState>> {
+        let calls = self.active_calls.read().await;
+        calls.get(call_id).map(|call| call.state_tx.subscribe())
     }
 
     /// Get active call count
@@ -395,26 +1247,46 @@ impl CallRouter {
     /// Get all active calls
     pub async fn get_active_calls(&self) -> Vec<ActiveCallInfo> {
         let calls = self.active_calls.read().await;
+        let moh_players = self.moh_players.read().await;
         let mut result = Vec::new();
 
         for call in calls.values() {
             let stats = call.state_machine.stats();
+            let now = std::time::Instant::now();
             let duration = stats.ended_at
-                .unwrap_or_else(|| std::time::Instant::now())
+                .unwrap_or(now)
                 .duration_since(stats.created_at)
                 .as_secs() as i64;
 
             let on_hold = self.hold_manager.is_on_hold(&call.call_id).await;
+            let (caller_ptime, callee_ptime) = Self::effective_ptime(call);
+            let (caller_rtcp_mux, callee_rtcp_mux) = Self::rtcp_mux(call);
+            let (caller_sdp_rtp, caller_latched_rtp) = Self::media_addresses(&call.caller);
+            let (callee_sdp_rtp, callee_latched_rtp) = Self::media_addresses(&call.callee);
 
             result.push(ActiveCallInfo {
                 call_id: call.call_id.clone(),
-                caller_uri: call.caller.uri.clone(),
-                callee_uri: call.callee.uri.clone(),
+                caller_uri: call.caller.uri.to_string(),
+                callee_uri: call.callee.uri.to_string(),
                 state: format!("{:?}", call.state()),
                 duration,
                 caller_contact: call.caller.contact.map(|c| c.to_string()),
                 callee_contact: call.callee.contact.map(|c| c.to_string()),
                 on_hold,
+                caller_ptime_ms: caller_ptime,
+                callee_ptime_ms: callee_ptime,
+                caller_rtcp_mux,
+                callee_rtcp_mux,
+                caller_sdp_rtp,
+                caller_latched_rtp,
+                callee_sdp_rtp,
+                callee_latched_rtp,
+                setup_secs: stats.setup_duration().map(|d| d.as_secs() as i64),
+                ring_secs: stats.ring_duration(now).map(|d| d.as_secs() as i64),
+                talk_secs: stats.talk_duration(now).as_secs() as i64,
+                hold_secs: stats.hold_duration(now).as_secs() as i64,
+                hold_count: stats.hold_count,
+                moh: moh_players.get(&call.call_id).and_then(|player| player.now_playing()),
             });
         }
 
@@ -424,30 +1296,174 @@ impl CallRouter {
     /// Get active call by ID
     pub async fn get_active_call(&self, call_id: &str) -> Option<ActiveCallInfo> {
         let calls = self.active_calls.read().await;
+        let moh_players = self.moh_players.read().await;
         if let Some(call) = calls.get(call_id) {
             let stats = call.state_machine.stats();
+            let now = std::time::Instant::now();
             let duration = stats.ended_at
-                .unwrap_or_else(|| std::time::Instant::now())
+                .unwrap_or(now)
                 .duration_since(stats.created_at)
                 .as_secs() as i64;
 
             let on_hold = self.hold_manager.is_on_hold(call_id).await;
+            let (caller_ptime, callee_ptime) = Self::effective_ptime(call);
+            let (caller_rtcp_mux, callee_rtcp_mux) = Self::rtcp_mux(call);
+            let (caller_sdp_rtp, caller_latched_rtp) = Self::media_addresses(&call.caller);
+            let (callee_sdp_rtp, callee_latched_rtp) = Self::media_addresses(&call.callee);
 
             Some(ActiveCallInfo {
                 call_id: call.call_id.clone(),
-                caller_uri: call.caller.uri.clone(),
-                callee_uri: call.callee.uri.clone(),
+                caller_uri: call.caller.uri.to_string(),
+                callee_uri: call.callee.uri.to_string(),
                 state: format!("{:?}", call.state()),
                 duration,
                 caller_contact: call.caller.contact.map(|c| c.to_string()),
                 callee_contact: call.callee.contact.map(|c| c.to_string()),
                 on_hold,
+                caller_ptime_ms: caller_ptime,
+                callee_ptime_ms: callee_ptime,
+                caller_rtcp_mux,
+                callee_rtcp_mux,
+                caller_sdp_rtp,
+                caller_latched_rtp,
+                callee_sdp_rtp,
+                callee_latched_rtp,
+                setup_secs: stats.setup_duration().map(|d| d.as_secs() as i64),
+                ring_secs: stats.ring_duration(now).map(|d| d.as_secs() as i64),
+                talk_secs: stats.talk_duration(now).as_secs() as i64,
+                hold_secs: stats.hold_duration(now).as_secs() as i64,
+                hold_count: stats.hold_count,
+                moh: moh_players.get(call_id).and_then(|player| player.now_playing()),
             })
         } else {
             None
         }
     }
 
+    /// RTCP multiplexing of the caller's and callee's media, from the legs'
+    /// streams or else the bridge
+    fn rtcp_mux(call: &BridgedCall) -> (Option<bool>, Option<bool>) {
+        let bridged = call.media_bridge.as_ref().map(|b| b.rtcp_mux());
+        let leg = |leg: &CallLegInfo, bridged: Option<bool>| {
+            leg.media_stream.as_ref().map(|s| s.is_rtcp_muxed()).or(bridged)
+        };
+        (
+            leg(&call.caller, bridged.map(|(a, _)| a)),
+            leg(&call.callee, bridged.map(|(_, b)| b)),
+        )
+    }
+
+    /// SDP and latched RTP address of a leg's media
+    fn media_addresses(leg: &CallLegInfo) -> (Option<String>, Option<String>) {
+        match &leg.media_stream {
+            Some(stream) => (
+                stream.sdp_remote().map(|a| a.to_string()),
+                stream.latched_remote().map(|a| a.to_string()),
+            ),
+            None => (None, None),
+        }
+    }
+
+    /// Packet time of the caller's and callee's media, from the bridge
+    fn effective_ptime(call: &BridgedCall) -> (Option<u32>, Option<u32>) {
+        match call.media_bridge.as_ref().and_then(|b| b.packet_formats()) {
+            Some((caller, callee)) => (Some(caller.ptime_ms), Some(callee.ptime_ms)),
+            None => (None, None),
+        }
+    }
+
+        /// Replication checkpoint of an active call
+    pub async fn checkpoint(&self, call_id: &str) -> Option<CallCheckpoint> {
+        let calls = self.active_calls.read().await;
+        calls.get(call_id).map(|call| self.call_checkpoint(call))
+    }
+
+    /// Replication checkpoints of all active calls
+    pub async fn checkpoints(&self) -> Vec<CallCheckpoint> {
+        let calls = self.active_calls.read().await;
+        calls.values().map(|call| self.call_checkpoint(call)).collect()
+    }
+
+    fn call_checkpoint(&self, call: &BridgedCall) -> CallCheckpoint {
+        CallCheckpoint {
+            call_id: call.call_id.clone(),
+            caller_uri: call.caller.uri.to_string(),
+            callee_uri: call.callee.uri.to_string(),
+            caller_contact: call.caller.contact,
+            callee_contact: call.callee.contact,
+            established: call.state().is_established(),
+            cdr_id: call.cdr_id,
+            local_cseq: self
+                .sequences
+                .as_ref()
+                .and_then(|vault| vault.cseq(&call.call_id))
+                .unwrap_or_default(),
+        }
+    }
+
+    /// Insert or update a call replicated from the active node
+    ///
+    /// No CDR or call event is recorded: the active node already did.
+    pub async fn restore_call(&self, checkpoint: CallCheckpoint) -> Result<(), String> {
+        let caller = SipUri::parse(&checkpoint.caller_uri)?;
+        let callee = SipUri::parse(&checkpoint.callee_uri)?;
+        {
+            let mut calls = self.active_calls.write().await;
+            let call = calls
+                .entry(checkpoint.call_id.clone())
+                .or_insert_with(|| {
+                    BridgedCall::new(checkpoint.call_id.clone(), caller, callee, checkpoint.cdr_id)
+                });
+            call.caller.contact = checkpoint.caller_contact;
+            call.callee.contact = checkpoint.callee_contact;
+            if checkpoint.established && !call.state().is_established() {
+                call.process_event(CallEvent::Answer)?;
+            }
+        }
+        if let Some(vault) = &self.sequences {
+            vault.restore_cseq(&checkpoint.call_id, checkpoint.local_cseq);
+        }
+        // Later transitions go on from the aggregate the active node saved
+        if let Some(events) = &self.call_events {
+            if let Err(e) = events.recover(&checkpoint.call_id, checkpoint.cdr_id).await {
+                debug!("No saved aggregate for restored call {}: {}", checkpoint.call_id, e);
+            }
+        }
+        Ok(())
+    }
+
+    /// Forget a replicated call that ended on the active node
+    pub async fn remove_restored_call(&self, call_id: &str) -> bool {
+        match self.active_calls.write().await.remove(call_id) {
+            Some(call) => {
+                self.release_call_resources(call_id, call).await;
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Replace all calls with those replicated from the active node
+    pub async fn restore_calls(&self, checkpoints: Vec<CallCheckpoint>) -> Result<(), String> {
+        let keep: std::collections::HashSet<String> =
+            checkpoints.iter().map(|c| c.call_id.clone()).collect();
+        let stale: Vec<String> = self
+            .active_calls
+            .read()
+            .await
+            .keys()
+            .filter(|call_id| !keep.contains(*call_id))
+            .cloned()
+            .collect();
+        for call_id in stale {
+            self.remove_restored_call(&call_id).await;
+        }
+        for checkpoint in checkpoints {
+            self.restore_call(checkpoint).await?;
+        }
+        Ok(())
+    }
+
     /// Force hangup a call (for admin/management use)
     pub async fn hangup_call(&self, call_id: &str) -> Result<(), String> {
         self.terminate_call(call_id).await
@@ -455,7 +1471,171 @@ impl CallRouter {
 
     /// Check if callee is available
     pub async fn is_callee_available(&self, callee_uri: &str) -> bool {
-        self.registrar.get_bindings(callee_uri).await.is_some()
+        match SipUri::parse(callee_uri) {
+            Ok(uri) => self.registrar.bindings_for_uri(&uri).await.is_some(),
+            Err(_) => false,
+        }
+    }
+
+    /// Store our media stream toward one party of a call
+    pub async fn set_leg_stream(&self, call_id: &str, leg: &CallLeg, stream: Arc<MediaStream>) {
+        let mut calls = self.active_calls.write().await;
+        if let Some(call) = calls.get_mut(call_id) {
+            call.leg_mut(leg).media_stream = Some(stream);
+        }
+    }
+
+    /// Switch the caller's audio on a provisional response from the callee
+    /// leg
+    ///
+    /// `early_media` is our stream toward the callee when the response is a
+    /// 183 with SDP. Following the ringback policy of the call's trunk and
+    /// tenant, the caller then hears that early media (bridged to the
+    /// caller's stream), ringback played here, or nothing; local ringback
+    /// stops as soon as early media takes over. The returned source's
+    /// `caller_status` is the provisional response to relay to the caller.
+    pub async fn callee_progress(
+        &self,
+        call_id: &str,
+        status: u16,
+        early_media: Option<Arc<MediaStream>>,
+        trunk: Option<&str>,
+    ) -> Result<RingbackSource, String> {
+        let mut calls = self.active_calls.write().await;
+        let call = calls
+            .get_mut(call_id)
+            .ok_or_else(|| format!("Call {} not found", call_id))?;
+        if !call.state().is_provisional() {
+            return Err(format!("Call {} is not alerting", call_id));
+        }
+        if call.ringback.is_none() {
+            let (policy, tone_plan) = self.ringback.resolve(trunk, realm_of(&call.caller.uri.to_string()));
+            call.ringback = Some(Ringback::new(policy, tone_plan));
+        }
+        let Some(ringback) = call.ringback.as_mut() else {
+            return Err(format!("Call {} has no ringback", call_id));
+        };
+        let previous = ringback.source();
+        let has_early_media = early_media.is_some();
+        let source = ringback.on_provisional(status, has_early_media);
+
+        if source == RingbackSource::EarlyMedia && previous != RingbackSource::EarlyMedia {
+            if let Some(early) = early_media {
+                if let Some(old) = call.callee.media_stream.replace(early.clone()) {
+                    if !Arc::ptr_eq(&old, &early) {
+                        old.close().await;
+                    }
+                }
+                let format = PacketFormat::new(0, DEFAULT_PTIME_MS);
+                let bridge = match (&call.media_bridge, &call.caller.media_stream) {
+                    (Some(bridge), _) => {
+                        let format = bridge.packet_formats().map_or(format, |(a, _)| a);
+                        let (bridge, old) = bridge.replace_leg(BridgeLeg::B, early, format).await;
+                        let caller_stream = call.caller.media_stream.as_ref();
+                        if !caller_stream.is_some_and(|caller| Arc::ptr_eq(caller, &old)) {
+                            old.close().await;
+                        }
+                        Some(bridge)
+                    }
+                    (None, Some(caller)) => Some(MediaBridge::new(caller.clone(), early)),
+                    (None, None) => None,
+                };
+                if let Some(bridge) = bridge {
+                    call.media_bridge = Some(Arc::new(bridge));
+                }
+            }
+        }
+        let caller_stream = call.caller.media_stream.clone();
+        let payload_type = call
+            .media_bridge
+            .as_ref()
+            .and_then(|bridge| bridge.packet_formats())
+            .map_or(0, |(caller, _)| caller.payload_type);
+        drop(calls);
+
+        // Timestamped for billing disputes; billing never starts here
+        if has_early_media {
+            if let Some(events) = &self.call_events {
+                if let Err(e) = events.early_media(call_id).await {
+                    warn!("Failed to record early media of call {}: {}", call_id, e);
+                }
+            }
+        }
+
+        if source != previous {
+            if let RingbackSource::LocalTone(_) = previous {
+                self.stop_ringback(call_id).await;
+            }
+            if let RingbackSource::LocalTone(tone_plan) = source {
+                let player = Arc::new(RingbackPlayer::new(tone_plan, payload_type));
+                if let Some(stream) = caller_stream {
+                    player.start(stream)?;
+                }
+                self.ringback_players
+                    .write()
+                    .await
+                    .insert(call_id.to_string(), player);
+            }
+            info!("Call {} ringback: {:?} -> {:?}", call_id, previous, source);
+        }
+        Ok(source)
+    }
+
+    /// What the caller of an alerting call hears
+    pub async fn ringback_source(&self, call_id: &str) -> Option<RingbackSource> {
+        let calls = self.active_calls.read().await;
+        calls
+            .get(call_id)
+            .and_then(|call| call.ringback.as_ref())
+            .map(Ringback::source)
+    }
+
+    /// Local ringback playing toward the caller of a call
+    pub async fn ringback_player(&self, call_id: &str) -> Option<Arc<RingbackPlayer>> {
+        self.ringback_players.read().await.get(call_id).cloned()
+    }
+
+    async fn stop_ringback(&self, call_id: &str) {
+        let player = self.ringback_players.write().await.remove(call_id);
+        if let Some(player) = player {
+            player.close();
+            debug!("Ringback stopped for call {}", call_id);
+        }
+    }
+
+    /// Record the dialog tags of a call: the caller's From tag and the To
+    /// tag of our answer
+    pub async fn set_dialog_tags(
+        &self,
+        call_id: &str,
+        caller_tag: Option<String>,
+        local_tag: String,
+    ) {
+        let mut calls = self.active_calls.write().await;
+        if let Some(call) = calls.get_mut(call_id) {
+            call.caller_tag = caller_tag;
+            call.local_tag = Some(local_tag);
+        }
+    }
+
+    /// Dialog tags of a call: the caller's From tag and the To tag of our
+    /// answer
+    pub async fn dialog_tags(&self, call_id: &str) -> Option<(Option<String>, Option<String>)> {
+        let calls = self.active_calls.read().await;
+        calls
+            .get(call_id)
+            .map(|call| (call.caller_tag.clone(), call.local_tag.clone()))
+    }
+
+    /// The call a Call-ID belongs to: the taken-over call for the Call-ID
+    /// of a device that took it over, else the Call-ID itself
+    pub async fn canonical_call_id(&self, call_id: &str) -> String {
+        self.aliases
+            .read()
+            .await
+            .get(call_id)
+            .cloned()
+            .unwrap_or_else(|| call_id.to_string())
     }
 
     /// Store caller contact for call
@@ -496,6 +1676,169 @@ impl CallRouter {
         }
     }
 
+    /// Flag the CDR of a call to a built-in test service, so it is not billed
+    pub async fn mark_test_call(&self, call_id: &str) {
+        let calls = self.active_calls.read().await;
+        if let (Some(call), Some(cdr_repo)) = (calls.get(call_id), &self.cdr_repository) {
+            if let Ok(Some(mut cdr)) = cdr_repo.get_by_id(call.cdr_id).await {
+                cdr.mark_test_call();
+                if let Err(e) = cdr_repo.update(&cdr).await {
+                    error!("Failed to flag CDR of test call {}: {}", call_id, e);
+                }
+            }
+        }
+    }
+
+    /// Record on the CDR of a call answered by an announcement-only number
+    /// which message `variant` it heard
+    pub async fn mark_announcement(&self, call_id: &str, variant: &str) {
+        let calls = self.active_calls.read().await;
+        if let (Some(call), Some(cdr_repo)) = (calls.get(call_id), &self.cdr_repository) {
+            if let Ok(Some(mut cdr)) = cdr_repo.get_by_id(call.cdr_id).await {
+                cdr.mark_announcement(variant.to_string());
+                if let Err(e) = cdr_repo.update(&cdr).await {
+                    error!("Failed to record announcement of call {}: {}", call_id, e);
+                }
+            }
+        }
+    }
+
+    /// Record on the CDR of an answered call that the caller's class of
+    /// service refused it
+    pub async fn mark_blocked_cos(&self, call_id: &str, reason: &str) {
+        let calls = self.active_calls.read().await;
+        if let (Some(call), Some(cdr_repo)) = (calls.get(call_id), &self.cdr_repository) {
+            if let Ok(Some(mut cdr)) = cdr_repo.get_by_id(call.cdr_id).await {
+                cdr.mark_blocked_cos(reason.to_string());
+                if let Err(e) = cdr_repo.update(&cdr).await {
+                    error!("Failed to record class of service block of call {}: {}", call_id, e);
+                }
+            }
+        }
+    }
+
+    /// Record on the CDR of a call that it is captioned live
+    pub async fn mark_live_transcribed(&self, call_id: &str) {
+        let calls = self.active_calls.read().await;
+        if let (Some(call), Some(cdr_repo)) = (calls.get(call_id), &self.cdr_repository) {
+            if let Ok(Some(mut cdr)) = cdr_repo.get_by_id(call.cdr_id).await {
+                cdr.mark_live_transcribed();
+                if let Err(e) = cdr_repo.update(&cdr).await {
+                    error!("Failed to record live transcription of call {}: {}", call_id, e);
+                }
+            }
+        }
+    }
+
+    /// Record on the CDR of a call why its media fell back to another codec
+    pub async fn record_codec_fallback(&self, call_id: &str, note: String) {
+        let calls = self.active_calls.read().await;
+        if let (Some(call), Some(cdr_repo)) = (calls.get(call_id), &self.cdr_repository) {
+            if let Ok(Some(mut cdr)) = cdr_repo.get_by_id(call.cdr_id).await {
+                cdr.record_codec_fallback(note);
+                if let Err(e) = cdr_repo.update(&cdr).await {
+                    error!("Failed to record codec fallback of call {}: {}", call_id, e);
+                }
+            }
+        }
+    }
+
+    /// Write the CDR of a call refused with 403 by the caller's class of
+    /// service, before it was routed
+    pub async fn record_blocked_cos(
+        &self,
+        call_id: &str,
+        caller_uri: &str,
+        callee_uri: &str,
+        dialed: Option<String>,
+        reason: &str,
+    ) {
+        let Some(cdr_repo) = &self.cdr_repository else {
+            return;
+        };
+        let mut cdr = CallDetailRecord::new(
+            call_id.to_string(),
+            Self::extract_username(caller_uri),
+            caller_uri.to_string(),
+            "0.0.0.0".to_string(),
+            Self::extract_username(callee_uri),
+            callee_uri.to_string(),
+            CallDirection::Outbound,
+        );
+        if let Some(dialed) = dialed {
+            cdr.set_dialed_number(dialed);
+        }
+        cdr.mark_ended(CallStatus::BlockedCos, Some(reason.to_string()), Some(403));
+        if let Err(e) = cdr_repo.create(&cdr).await {
+            error!("Failed to create CDR of blocked call {}: {}", call_id, e);
+        }
+    }
+
+    /// Take a channel of `trunk` for an inbound call and record the trunk
+    /// on its CDR; refused when no channel is free for inbound calls
+    pub async fn seize_inbound_trunk(&self, call_id: &str, trunk: &str) -> Result<(), TrunkFull> {
+        if let Some(trunks) = &self.trunks {
+            trunks.acquire(call_id, trunk, ChannelDirection::Inbound)?;
+        }
+        self.set_trunk(call_id, trunk).await;
+        Ok(())
+    }
+
+    /// Record on the CDR of a call the trunk it came in from or went out
+    /// through, which billing supervises it by
+    pub async fn set_trunk(&self, call_id: &str, trunk: &str) {
+        let mut calls = self.active_calls.write().await;
+        if let Some(call) = calls.get_mut(call_id) {
+            call.trunk = Some(trunk.to_string());
+        }
+        if let (Some(call), Some(cdr_repo)) = (calls.get(call_id), &self.cdr_repository) {
+            if let Ok(Some(mut cdr)) = cdr_repo.get_by_id(call.cdr_id).await {
+                if cdr.trunk.as_deref() == Some(trunk) {
+                    return;
+                }
+                cdr.set_trunk(trunk.to_string());
+                if let Err(e) = cdr_repo.update(&cdr).await {
+                    error!("Failed to record trunk of call {}: {}", call_id, e);
+                }
+            }
+        }
+    }
+
+    /// Record on the CDR of a call the resolved target that answered it
+    async fn set_served_by(&self, call_id: &str, target: &SipTarget) {
+        debug!("Call {} served by {}", call_id, target);
+        let calls = self.active_calls.read().await;
+        if let (Some(call), Some(cdr_repo)) = (calls.get(call_id), &self.cdr_repository) {
+            if let Ok(Some(mut cdr)) = cdr_repo.get_by_id(call.cdr_id).await {
+                cdr.set_callee_ip(target.addr.ip().to_string());
+                if let Err(e) = cdr_repo.update(&cdr).await {
+                    error!("Failed to record callee address of call {}: {}", call_id, e);
+                }
+            }
+        }
+    }
+
+    /// The caller's ACK completed the answer of a call
+    ///
+    /// ACKs of calls that are not established (e.g. of a failure response)
+    /// and retransmitted ACKs are ignored.
+    pub async fn acknowledge_call(&self, call_id: &str) {
+        let established = self
+            .active_calls
+            .read()
+            .await
+            .get(call_id)
+            .is_some_and(|call| *call.state() == CallState::Established);
+        if !established {
+            return;
+        }
+        if let Some(events) = &self.call_events {
+            if let Err(e) = events.acknowledge(call_id).await {
+                warn!("Failed to record ACK of call {}: {}", call_id, e);
+            }
+        }
+    }
+
     /// Get caller contact for forwarding responses
     pub async fn get_caller_contact(&self, call_id: &str) -> Option<SocketAddr> {
         let calls = self.active_calls.read().await;
@@ -552,16 +1895,11 @@ impl CallRouter {
                 // Process reject event to transition to Failed state
                 call.process_event(CallEvent::Reject)?;
                 info!("Call {} cancelled", call_id);
-
-                // Update CDR with cancellation
-                if let Some(ref cdr_repo) = self.cdr_repository {
-                    if let Ok(Some(mut cdr)) = cdr_repo.get_by_id(call.cdr_id).await {
-                        cdr.mark_ended(CallStatus::Cancelled, Some("Call cancelled".to_string()), Some(487));
-                        if let Err(e) = cdr_repo.update(&cdr).await {
-                            error!("Failed to update CDR on cancel: {}", e);
-                        }
-                    }
+                self.report_call_ended(call_id, call);
+                if let Some(setup) = call.setup {
+                    self.record_setup_latency(call_id, call.cdr_id, &setup).await;
                 }
+                self.record_call_ended(call_id, EndReason::Canceled).await;
 
                 Ok(true)
             } else if state == &CallState::Established {
@@ -623,9 +1961,48 @@ impl CallRouter {
 
         // Mark call as on hold in hold manager
         self.hold_manager.hold_call(call_id).await?;
+        self.dialog_manager
+            .with_dialog(call_id, false, |d| d.set_local_hold(true))
+            .await;
+        self.reinvite_caller(call_id).await;
 
-        // Start music on hold
-        let moh_player = Arc::new(MohPlayer::new());
+        // Start music on hold: the class of the call's queue or tenant,
+        // else the tenant's own file, else the default class or source
+        let (queue_class, tenant) = self
+            .active_calls
+            .read()
+            .await
+            .get(call_id)
+            .map(|call| {
+                let tenant = self.branding.as_ref().and_then(|branding| {
+                    branding.tenant_of(&call.caller.uri.to_string(), &call.callee.uri.to_string())
+                });
+                (call.moh_class.clone(), tenant)
+            })
+            .unwrap_or_default();
+        let tenant_branding = tenant
+            .as_deref()
+            .zip(self.branding.as_ref())
+            .and_then(|(tenant, branding)| branding.get(tenant));
+        let moh_class = self.moh_classes.as_ref().and_then(|classes| {
+            let names = [
+                queue_class.as_deref(),
+                tenant_branding.as_ref().and_then(|b| b.moh_class.as_deref()),
+            ];
+            if tenant_branding.as_ref().is_some_and(|b| b.moh_file.is_some()) {
+                classes.find(&names)
+            } else {
+                classes.select(&names)
+            }
+        });
+        let moh_player = match (moh_class, &self.branding) {
+            (Some(class), _) => Arc::new(MohPlayer::with_class(class, rand::random())),
+            (None, Some(branding)) => Arc::new(MohPlayer::with_config(MohConfig {
+                source: branding.moh_source(tenant.as_deref()),
+                ..Default::default()
+            })),
+            (None, None) => Arc::new(MohPlayer::new()),
+        };
         if let Err(e) = moh_player.start().await {
             warn!("Failed to start MOH for call {}: {}", call_id, e);
         } else {
@@ -638,10 +2015,78 @@ impl CallRouter {
         // TODO: Update media stream direction to sendonly
         // This requires accessing the media stream and changing its direction
 
+        self.track_hold(call_id).await;
+
         info!("Call {} placed on hold with MOH", call_id);
         Ok(())
     }
 
+    /// Put a call on hold on behalf of the party at `holder_uri`
+    ///
+    /// The holder is who hold reminders and recovery go to.
+    pub async fn hold_call_from(&self, call_id: &str, holder_uri: &str) -> Result<(), String> {
+        self.hold_call(call_id).await?;
+        if let Some(call) = self.active_calls.write().await.get_mut(call_id) {
+            let caller_holds =
+                SipUri::parse(holder_uri).is_ok_and(|uri| uri.equivalent(&call.caller.uri));
+            call.holder = Some(if caller_holds {
+                CallLeg::Caller
+            } else {
+                CallLeg::Callee
+            });
+        }
+        Ok(())
+    }
+
+    /// Calls on local hold, except those waiting for a blind transfer or
+    /// a warm transfer's consultation (transfers have their own deadlines)
+    pub async fn held_calls(&self) -> Vec<HeldCall> {
+        let holds = self.hold_manager.local_holds().await;
+        let calls = self.active_calls.read().await;
+        holds
+            .into_iter()
+            .filter_map(|(call_id, held_since)| {
+                let call = calls.get(&call_id)?;
+                if call.pending_transfer.is_some() || call.consultation.is_some() {
+                    return None;
+                }
+                let holder = call.holder.clone().unwrap_or(CallLeg::Callee);
+                let held = match holder {
+                    CallLeg::Caller => &call.callee,
+                    CallLeg::Callee => &call.caller,
+                };
+                Some(HeldCall {
+                    call_id,
+                    holder_uri: call.leg(&holder).uri.to_string(),
+                    held_uri: held.uri.to_string(),
+                    held_since,
+                })
+            })
+            .collect()
+    }
+
+    /// Media streams of the legs of established calls
+    pub async fn established_media(&self) -> Vec<LegMedia> {
+        let calls = self.active_calls.read().await;
+        calls
+            .values()
+            .filter(|call| call.state().is_established())
+            .flat_map(|call| {
+                [CallLeg::Caller, CallLeg::Callee]
+                    .into_iter()
+                    .filter_map(|leg| {
+                        let stream = call.leg(&leg).media_stream.clone()?;
+                        Some(LegMedia {
+                            call_id: call.call_id.clone(),
+                            leg,
+                            stream,
+                        })
+                    })
+                    .collect::<Vec<_>>()
+            })
+            .collect()
+    }
+
     /// Resume call from hold
     ///
     /// This will mark the call as active and restore media stream direction
@@ -657,34 +2102,86 @@ impl CallRouter {
 
         // Resume call in hold manager
         self.hold_manager.resume_call(call_id).await?;
+        if let Some(call) = self.active_calls.write().await.get_mut(call_id) {
+            call.holder = None;
+        }
+        self.dialog_manager
+            .with_dialog(call_id, false, |d| d.set_local_hold(false))
+            .await;
+        self.reinvite_caller(call_id).await;
 
         // Stop music on hold
         {
             let mut moh_players = self.moh_players.write().await;
             if let Some(moh_player) = moh_players.remove(call_id) {
-                if let Err(e) = moh_player.stop().await {
-                    warn!("Failed to stop MOH for call {}: {}", call_id, e);
-                } else {
-                    debug!("Stopped MOH for call {}", call_id);
-                }
+                moh_player.close().await;
+                debug!("Stopped MOH for call {}", call_id);
             }
         }
 
         // TODO: Update media stream direction to sendrecv
         // This requires accessing the media stream and changing its direction
 
+        self.track_hold(call_id).await;
+
         info!("Call {} resumed from hold", call_id);
         Ok(())
     }
 
+    /// Offer the caller our new hold state in a re-INVITE, if we can send one
+    async fn reinvite_caller(&self, call_id: &str) {
+        let Some(sender) = &self.reinvite_sender else {
+            return;
+        };
+        match self
+            .dialog_manager
+            .reinvite(call_id, &CallLeg::Caller, sender.as_ref())
+            .await
+        {
+            Some(LocalReinviteOutcome::Completed) => {
+                debug!("Call {} hold state re-negotiated", call_id)
+            }
+            Some(outcome) => warn!("Hold re-INVITE of call {} failed: {:?}", call_id, outcome),
+            None => debug!("Call {} has no SDP to re-offer yet", call_id),
+        }
+    }
+
     /// Mark remote party as holding (detected from re-INVITE with sendonly/recvonly SDP)
     pub async fn remote_hold(&self, call_id: &str) -> Result<(), String> {
-        self.hold_manager.remote_hold(call_id).await
+        self.hold_manager.remote_hold(call_id).await?;
+        self.track_hold(call_id).await;
+        Ok(())
     }
 
     /// Mark remote party as resuming (detected from re-INVITE with sendrecv SDP)
     pub async fn remote_resume(&self, call_id: &str) -> Result<(), String> {
-        self.hold_manager.remote_resume(call_id).await
+        self.hold_manager.remote_resume(call_id).await?;
+        self.track_hold(call_id).await;
+        Ok(())
+    }
+
+    /// Pause or resume the talk time of a call as it goes on or off hold,
+    /// by either party, and record the hold or resume
+    ///
+    /// A call held by both parties is resumed only once neither holds it.
+    async fn track_hold(&self, call_id: &str) {
+        let on_hold = self.hold_manager.is_on_hold(call_id).await;
+        let now = std::time::Instant::now();
+        let changed = match self.active_calls.write().await.get_mut(call_id) {
+            Some(call) if on_hold => call.state_machine.hold_at(now),
+            Some(call) => call.state_machine.resume_at(now),
+            None => false,
+        };
+        let Some(events) = self.call_events.as_ref().filter(|_| changed) else {
+            return;
+        };
+        if on_hold {
+            if let Err(e) = events.hold(call_id).await {
+                warn!("Failed to record hold of call {}: {}", call_id, e);
+            }
+        } else if let Err(e) = events.resume(call_id).await {
+            warn!("Failed to record resume of call {}: {}", call_id, e);
+        }
     }
 
     /// Get hold manager reference (for advanced use cases)
@@ -692,30 +2189,240 @@ impl CallRouter {
         self.hold_manager.clone()
     }
 
+    /// Get dialog offer/answer state manager
+    pub fn dialog_manager(&self) -> Arc<DialogManager> {
+        self.dialog_manager.clone()
+    }
+
     /// Check if call is on hold
     pub async fn is_call_on_hold(&self, call_id: &str) -> bool {
         self.hold_manager.is_on_hold(call_id).await
     }
 
-    /// Blind transfer - transfer call to another party without consultation
+    /// Play the MOH class `class` when the call is put on hold, e.g. the
+    /// one of the queue it waits in; `None` falls back to the tenant's
+    pub async fn set_moh_class(&self, call_id: &str, class: Option<String>) -> Result<(), String> {
+        let mut calls = self.active_calls.write().await;
+        let call = calls
+            .get_mut(call_id)
+            .ok_or_else(|| format!("Call {} not found", call_id))?;
+        call.moh_class = class;
+        Ok(())
+    }
+
+    /// Source of the MOH playing to a held call
+    pub async fn moh_source(&self, call_id: &str) -> Option<String> {
+        self.moh_players
+            .read()
+            .await
+            .get(call_id)
+            .map(|player| player.source().to_string())
+    }
+
+    /// Start screening a new call if its callee screens its caller
     ///
-    /// # Arguments
-    /// * `call_id` - The call to transfer
+    /// The caller is answered by the PBX (no answer is recorded for the
+    /// call) and gets the prompt returned here to record their name.
+    pub async fn screen_call(&self, call_id: &str) -> Result<Option<AnnouncementRequest>, String> {
+        let screening = match &self.screening {
+            Some(screening) => screening,
+            None => return Ok(None),
+        };
+        let mut calls = self.active_calls.write().await;
+        let call = calls
+            .get_mut(call_id)
+            .ok_or_else(|| format!("Call {} not found", call_id))?;
+        let (caller_uri, callee_uri) = (call.caller.uri.to_string(), call.callee.uri.to_string());
+        let flow = match screening.start(call_id, &caller_uri, &callee_uri) {
+            Some(flow) => flow,
+            None => return Ok(None),
+        };
+        let prompt = flow.record_name_prompt(screening.policy());
+        info!("Screening call {} to {}", call_id, call.callee.uri);
+        call.screening = Some(flow);
+        Ok(Some(prompt))
+    }
+
+    /// The call is being screened and its legs are not bridged yet
+    pub async fn is_screening(&self, call_id: &str) -> bool {
+        self.active_calls
+            .read()
+            .await
+            .get(call_id)
+            .and_then(|call| call.screening.as_ref())
+            .is_some_and(|flow| flow.end_reason().is_none())
+    }
+
+    /// The caller's name is recorded at `recording`; the callee is rung
+    pub async fn screening_name_recorded(&self, call_id: &str, recording: &str) -> Result<(), String> {
+        let mut calls = self.active_calls.write().await;
+        let call = calls
+            .get_mut(call_id)
+            .ok_or_else(|| format!("Call {} not found", call_id))?;
+        call.screening
+            .as_mut()
+            .ok_or_else(|| format!("Call {} is not screened", call_id))?
+            .name_recorded(recording)?;
+        call.process_event(CallEvent::Ringing)?;
+        if let Some(events) = &self.call_events {
+            if let Err(e) = events.ring(call_id).await {
+                warn!("Failed to record ringing of call {}: {}", call_id, e);
+            }
+        }
+        Ok(())
+    }
+
+    /// The screened callee answered; their leg stays apart from the
+    /// caller, who keeps hearing ringback. Returns the prompt for the callee.
+    pub async fn screened_callee_answered(&self, call_id: &str) -> Result<AnnouncementRequest, String> {
+        let screening = self
+            .screening
+            .as_ref()
+            .ok_or_else(|| "Call screening not enabled".to_string())?;
+        let mut calls = self.active_calls.write().await;
+        calls
+            .get_mut(call_id)
+            .and_then(|call| call.screening.as_mut())
+            .ok_or_else(|| format!("Call {} is not screened", call_id))?
+            .callee_answered(screening.policy())
+    }
+
+    /// Apply a digit pressed by the screened callee; `None` when it is not
+    /// one of the options
+    pub async fn screening_choice(&self, call_id: &str, digit: char) -> Result<Option<ScreeningOutcome>, String> {
+        self.settle_screening(call_id, |flow| flow.choose(digit)).await
+    }
+
+    /// The screened callee did not choose in time: the call goes to voicemail
+    pub async fn screening_timed_out(&self, call_id: &str) -> Result<Option<ScreeningOutcome>, String> {
+        self.settle_screening(call_id, ScreeningFlow::choice_timed_out)
+            .await
+    }
+
+    async fn settle_screening(
+        &self,
+        call_id: &str,
+        decide: impl FnOnce(&mut ScreeningFlow) -> Option<ScreeningChoice>,
+    ) -> Result<Option<ScreeningOutcome>, String> {
+        let screening = self
+            .screening
+            .as_ref()
+            .ok_or_else(|| "Call screening not enabled".to_string())?;
+        let mut calls = self.active_calls.write().await;
+        let call = calls
+            .get_mut(call_id)
+            .ok_or_else(|| format!("Call {} not found", call_id))?;
+        let flow = call
+            .screening
+            .as_mut()
+            .ok_or_else(|| format!("Call {} is not screened", call_id))?;
+        let choice = decide(flow);
+        let choice = match choice {
+            Some(choice) => choice,
+            None => return Ok(None),
+        };
+        info!("Screened call {}: {:?}", call_id, choice);
+
+        match choice {
+            ScreeningChoice::Accept => {
+                call.screening = None;
+                drop(calls);
+                self.answer_call(call_id).await?;
+                Ok(Some(ScreeningOutcome::Accepted))
+            }
+            ScreeningChoice::Voicemail => {
+                let target = screening.policy().voicemail_target(&call.callee.uri.to_string());
+                let name_recording = flow.name_recording().map(str::to_string);
+                // Voicemail takes the caller; the call is not recorded as answered
+                call.process_event(CallEvent::Answer)?;
+                let stream = {
+                    let callee = call.leg_mut(&CallLeg::Callee);
+                    callee.retarget(&target);
+                    callee.contact = None;
+                    callee.media_stream.take()
+                };
+                drop(calls);
+                if let Some(stream) = stream {
+                    stream.close().await;
+                }
+                Ok(Some(ScreeningOutcome::Voicemail {
+                    target,
+                    name_recording,
+                }))
+            }
+            ScreeningChoice::Reject => {
+                let prompt = flow.declined_prompt(screening.policy());
+                call.screening = None;
+                drop(calls);
+                self.reject_call(call_id, "Declined").await?;
+                Ok(Some(ScreeningOutcome::Rejected { prompt }))
+            }
+        }
+    }
+
+    /// Blind transfer - transfer call to another party without consultation
+    ///
+    /// The callee is taken to be the transferor; see
+    /// [`blind_transfer_from`](Self::blind_transfer_from).
+    ///
+    /// # Arguments
+    /// * `call_id` - The call to transfer
     /// * `target_uri` - The URI to transfer to (from Refer-To header)
     ///
     /// # Returns
     /// Ok(()) if transfer was initiated successfully
     pub async fn blind_transfer(&self, call_id: &str, target_uri: &str) -> Result<(), String> {
-        // Check if call exists and is established
+        self.start_transfer(call_id, None, target_uri).await
+    }
+
+    /// Blind transfer requested by the party at `transferor_uri`
+    ///
+    /// The transferee is put on hold with MOH and the transfer is recorded
+    /// on the call until [`execute_transfer`](Self::execute_transfer)
+    /// settles it or its deadline passes.
+    pub async fn blind_transfer_from(
+        &self,
+        call_id: &str,
+        transferor_uri: &str,
+        target_uri: &str,
+    ) -> Result<(), String> {
+        self.start_transfer(call_id, Some(transferor_uri), target_uri)
+            .await
+    }
+
+    async fn start_transfer(
+        &self,
+        call_id: &str,
+        transferor_uri: Option<&str>,
+        target_uri: &str,
+    ) -> Result<(), String> {
         {
-            let calls = self.active_calls.read().await;
-            if let Some(call) = calls.get(call_id) {
-                if !call.state().is_established() {
-                    return Err("Call must be established to be transferred".to_string());
-                }
-            } else {
-                return Err(format!("Call {} not found", call_id));
+            let mut calls = self.active_calls.write().await;
+            let call = calls
+                .get_mut(call_id)
+                .ok_or_else(|| format!("Call {} not found", call_id))?;
+            if !call.state().is_established() {
+                return Err("Call must be established to be transferred".to_string());
             }
+            if call.pending_transfer.is_some() {
+                return Err(format!("Call {} already has a transfer in progress", call_id));
+            }
+
+            let transferor = match transferor_uri {
+                Some(uri)
+                    if SipUri::parse(uri).is_ok_and(|uri| uri.equivalent(&call.caller.uri)) =>
+                {
+                    CallLeg::Caller
+                }
+                _ => CallLeg::Callee,
+            };
+            let transferor_uri = call.leg(&transferor).uri.to_string();
+            call.pending_transfer = Some(PendingTransfer::new(
+                target_uri.to_string(),
+                transferor,
+                transferor_uri,
+                &self.transfer_policy,
+            ));
         }
 
         info!(
@@ -723,30 +2430,659 @@ impl CallRouter {
             call_id, target_uri
         );
 
-        // In a real implementation, we would:
-        // 1. Send NOTIFY to transferor with "SIP/2.0 100 Trying"
-        // 2. Create new INVITE to target
-        // 3. Wait for target response
-        // 4. Send NOTIFY to transferor with final status
-        // 5. Bridge original caller with target
-        // 6. Terminate transferor's leg
-
-        // For now, just mark as successful
-        // TODO: Implement actual call bridging and NOTIFY sending
+        // The transferee waits on MOH while the target is rung (unless
+        // already held, e.g. by a hold recovery)
+        let held = matches!(
+            self.hold_manager.get_state(call_id).await,
+            Some(HoldState::LocalHold | HoldState::BothHold)
+        );
+        if held {
+            debug!("Call {} already on hold for transfer", call_id);
+        } else if let Err(e) = self.hold_call(call_id).await {
+            warn!("Failed to hold transferee of call {}: {}", call_id, e);
+        }
 
         info!("Blind transfer initiated for call {}", call_id);
         Ok(())
     }
 
+    /// Pending transfer of a call
+    pub async fn pending_transfer(&self, call_id: &str) -> Option<PendingTransfer> {
+        let calls = self.active_calls.read().await;
+        calls.get(call_id).and_then(|call| call.pending_transfer.clone())
+    }
+
+    /// Release the transferor's leg of a call with a pending transfer
+    ///
+    /// Returns false when `from_uri` is not the transferor of a pending
+    /// transfer; the BYE then ends the whole call as usual. Otherwise the
+    /// call stays up so the transfer, or its recovery, can still reach the
+    /// transferee. A transferor a warm transfer already replaced is let go
+    /// the same way.
+    pub async fn release_transferor(&self, call_id: &str, from_uri: &str) -> bool {
+        let stream = {
+            let mut calls = self.active_calls.write().await;
+            let call = match calls.get_mut(call_id) {
+                Some(call) => call,
+                None => return false,
+            };
+            let spliced_out = call.spliced_out.as_ref().is_some_and(|transferor| {
+                SipUri::parse(from_uri).is_ok_and(|from| from.equivalent(transferor))
+            });
+            if spliced_out {
+                debug!("Replaced transferor {} left call {}", from_uri, call_id);
+                return true;
+            }
+            let transferor = match &mut call.pending_transfer {
+                Some(transfer) if transfer.transferor_uri == from_uri => {
+                    transfer.transferor_released = true;
+                    transfer.transferor.clone()
+                }
+                _ => return false,
+            };
+            let leg = call.leg_mut(&transferor);
+            leg.contact = None;
+            leg.media_stream.take()
+        };
+        if let Some(stream) = stream {
+            stream.close().await;
+        }
+        info!("Transferor left call {}; transfer continues", call_id);
+        true
+    }
+
+    /// Keep the caller of an established call connected when the callee
+    /// (the agent) hangs up, for a post-call survey
+    pub async fn arm_survey(&self, call_id: &str) -> Result<(), String> {
+        let mut calls = self.active_calls.write().await;
+        let call = calls
+            .get_mut(call_id)
+            .ok_or_else(|| format!("Call {} not found", call_id))?;
+        if !call.state().is_established() {
+            return Err(format!("Call {} is not established", call_id));
+        }
+        call.survey = true;
+        Ok(())
+    }
+
+    /// Release the callee's leg of a call armed for a survey
+    ///
+    /// Returns false when the call is not armed or `from_uri` is not its
+    /// callee; the BYE then ends the whole call as usual. Otherwise the
+    /// legs are unbridged and the caller stays connected to the PBX.
+    pub async fn release_agent(&self, call_id: &str, from_uri: &str) -> bool {
+        let (bridge, stream) = {
+            let mut calls = self.active_calls.write().await;
+            let call = match calls.get_mut(call_id) {
+                Some(call) if call.survey => call,
+                _ => return false,
+            };
+            let is_callee = SipUri::parse(from_uri).is_ok_and(|from| from.equivalent(&call.callee.uri));
+            if !is_callee {
+                return false;
+            }
+            call.survey = false;
+            call.callee.contact = None;
+            (call.media_bridge.take(), call.callee.media_stream.take())
+        };
+        if let Some(bridge) = bridge {
+            bridge.close().await;
+        }
+        if let Some(stream) = stream {
+            stream.close().await;
+        }
+        info!("Agent left call {}; caller stays for the survey", call_id);
+        true
+    }
+
+    /// Invite the target of a pending blind transfer and settle it
+    ///
+    /// The transferor gets a `100 Trying` NOTIFY and then the target's final
+    /// status, before their leg is released. If the target fails or does
+    /// not answer before the transfer's deadline, the transferee is
+    /// reconnected to the recovery destination (the transferor unless a
+    /// fallback is configured); the call ends only if that fails too.
+    pub async fn execute_transfer(
+        &self,
+        call_id: &str,
+        forwarder: &dyn InviteForwarder,
+        notifier: &dyn TransferNotifier,
+    ) -> Result<TransferOutcome, String> {
+        let (transfer, transferee_uri) = {
+            let calls = self.active_calls.read().await;
+            let call = calls
+                .get(call_id)
+                .ok_or_else(|| format!("Call {} not found", call_id))?;
+            let transfer = call
+                .pending_transfer
+                .clone()
+                .ok_or_else(|| format!("Call {} has no pending transfer", call_id))?;
+            let transferee = match transfer.transferor {
+                CallLeg::Caller => &call.callee,
+                CallLeg::Callee => &call.caller,
+            };
+            (transfer, transferee.uri.to_string())
+        };
+
+        self.notify_transferor(call_id, notifier, 100).await;
+
+        let invite = transfer_invite(
+            call_id,
+            &transferee_uri,
+            &transfer.transferor_uri,
+            &transfer.target,
+        )
+        .map_err(|e| e.to_string())?;
+        // The target's provisional responses are reported as they arrive
+        let (progress, mut provisional) = mpsc::unbounded_channel();
+        let status = {
+            let target_status = Self::invite_within(
+                forwarder,
+                &transfer.target,
+                &invite,
+                transfer.remaining(),
+                progress,
+            );
+            tokio::pin!(target_status);
+            loop {
+                tokio::select! {
+                    status = &mut target_status => break status,
+                    Some(status) = provisional.recv() => {
+                        self.notify_transferor(call_id, notifier, status).await;
+                    }
+                }
+            }
+        };
+        if !self.active_calls.read().await.contains_key(call_id) {
+            return Err(format!("Call {} ended during transfer", call_id));
+        }
+
+        if (200..300).contains(&status) {
+            self.notify_transferor(call_id, notifier, status).await;
+            self.reconnect_transferee(call_id, &transfer.target).await;
+            info!("Call {} transferred to {}", call_id, transfer.target);
+            return Ok(TransferOutcome::Completed {
+                target: transfer.target,
+            });
+        }
+
+        // The failure is reported before the transferor's leg goes away
+        self.notify_transferor(call_id, notifier, status).await;
+        self.release_transferor(call_id, &transfer.transferor_uri).await;
+        warn!(
+            "Transfer of call {} to {} failed ({}), recovering to {}",
+            call_id, transfer.target, status, transfer.recovery_target
+        );
+
+        let invite = transfer_invite(
+            call_id,
+            &transferee_uri,
+            &transfer.transferor_uri,
+            &transfer.recovery_target,
+        )
+        .map_err(|e| e.to_string())?;
+        let recovery_status = Self::invite_within(
+            forwarder,
+            &transfer.recovery_target,
+            &invite,
+            self.transfer_policy.timeout(),
+            mpsc::unbounded_channel().0,
+        )
+        .await;
+
+        if (200..300).contains(&recovery_status) {
+            self.reconnect_transferee(call_id, &transfer.recovery_target)
+                .await;
+            info!(
+                "Transferee of call {} reconnected to {}",
+                call_id, transfer.recovery_target
+            );
+            Ok(TransferOutcome::Recovered {
+                target: transfer.recovery_target,
+                failed_status: status,
+            })
+        } else {
+            warn!(
+                "Recovery of call {} to {} failed ({}), ending call",
+                call_id, transfer.recovery_target, recovery_status
+            );
+            self.terminate_call(call_id).await?;
+            Ok(TransferOutcome::Failed {
+                failed_status: status,
+            })
+        }
+    }
+
+    /// Final status of an INVITE; 408 when no answer within `timeout`,
+    /// 503 when the target is unreachable
+    async fn invite_within(
+        forwarder: &dyn InviteForwarder,
+        target: &str,
+        invite: &SipRequest,
+        timeout: std::time::Duration,
+        progress: mpsc::UnboundedSender<u16>,
+    ) -> u16 {
+        let forward = forwarder.forward_with_progress(target, invite, progress);
+        match tokio::time::timeout(timeout, forward).await {
+            Ok(Ok(response)) => response.status_code(),
+            Ok(Err(e)) => {
+                warn!("Transfer target {} unreachable: {}", target, e);
+                503
+            }
+            Err(_) => {
+                warn!("Transfer target {} did not answer in time", target);
+                408
+            }
+        }
+    }
+
+    /// Report target status `status` on the REFER's subscription, unless
+    /// the transferor hung up, declined the subscription or it has ended
+    async fn notify_transferor(&self, call_id: &str, notifier: &dyn TransferNotifier, status: u16) {
+        let (transferor_uri, notify) = {
+            let mut calls = self.active_calls.write().await;
+            let transfer = match calls
+                .get_mut(call_id)
+                .and_then(|call| call.pending_transfer.as_mut())
+            {
+                Some(transfer) if !transfer.transferor_released => transfer,
+                _ => return,
+            };
+            let notify = match transfer.subscription.as_mut() {
+                Some(subscription) => match subscription.notify(status) {
+                    Some(notify) => notify,
+                    None => return,
+                },
+                None => return,
+            };
+            (transfer.transferor_uri.clone(), notify)
+        };
+        if let Err(e) = notifier.notify(call_id, &transferor_uri, &notify).await {
+            warn!("Failed to notify transferor of call {}: {}", call_id, e);
+        }
+    }
+
+    /// Drop the REFER's implicit subscription (`Refer-Sub: false`); the
+    /// transfer goes on without NOTIFYs
+    pub async fn decline_refer_subscription(&self, call_id: &str) -> bool {
+        let mut calls = self.active_calls.write().await;
+        match calls
+            .get_mut(call_id)
+            .and_then(|call| call.pending_transfer.as_mut())
+        {
+            Some(transfer) => {
+                transfer.subscription = None;
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Refresh the REFER's subscription on a SUBSCRIBE from the transferor
+    ///
+    /// Returns the NOTIFY answering the SUBSCRIBE (terminated when
+    /// `expires` is zero); fails when the call has no live subscription.
+    pub async fn refresh_refer_subscription(
+        &self,
+        call_id: &str,
+        expires: std::time::Duration,
+    ) -> Result<(String, ReferNotify), String> {
+        let mut calls = self.active_calls.write().await;
+        let transfer = calls
+            .get_mut(call_id)
+            .and_then(|call| call.pending_transfer.as_mut())
+            .ok_or_else(|| format!("Call {} has no pending transfer", call_id))?;
+        let subscription = transfer
+            .subscription
+            .as_mut()
+            .ok_or_else(|| format!("Transfer of call {} has no subscription", call_id))?;
+        let notify = subscription.refresh(expires)?;
+        Ok((transfer.transferor_uri.clone(), notify))
+    }
+
+    /// Put `uri` in place of the transferor and take the transferee off hold
+    async fn reconnect_transferee(&self, call_id: &str, uri: &str) {
+        let stream = {
+            let mut calls = self.active_calls.write().await;
+            let call = match calls.get_mut(call_id) {
+                Some(call) => call,
+                None => return,
+            };
+            let transfer = match call.pending_transfer.take() {
+                Some(transfer) => transfer,
+                None => return,
+            };
+            let leg = call.leg_mut(&transfer.transferor);
+            leg.retarget(uri);
+            leg.contact = None;
+            leg.media_stream.take()
+        };
+        if let Some(stream) = stream {
+            stream.close().await;
+        }
+
+        if let Err(e) = self.resume_call(call_id).await {
+            warn!("Failed to resume transferee of call {}: {}", call_id, e);
+        }
+    }
+
+    /// Start consulting `consult_id` for a warm transfer of `call_id`
+    ///
+    /// The transferor's leg is the one at `transferor_uri`, or the callee's
+    /// when not given; the other party is put on hold with MOH until the
+    /// consultation is spliced in or ended. Returns the transferor's and the
+    /// transferee's URIs.
+    pub async fn begin_consultation(
+        &self,
+        call_id: &str,
+        consult_id: &str,
+        transferor_uri: Option<&str>,
+    ) -> Result<(String, String), String> {
+        let (transferor, transferee) = {
+            let mut calls = self.active_calls.write().await;
+            let call = calls
+                .get_mut(call_id)
+                .ok_or_else(|| format!("Call {} not found", call_id))?;
+            if !call.state().is_established() {
+                return Err("Call must be established to be transferred".to_string());
+            }
+            if call.consultation.is_some() || call.pending_transfer.is_some() {
+                return Err(format!("Call {} is already being transferred", call_id));
+            }
+            let transferor = match transferor_uri {
+                Some(uri) => {
+                    let uri = SipUri::parse(uri)
+                        .map_err(|e| format!("Invalid transferor URI '{}': {}", uri, e))?;
+                    [CallLeg::Callee, CallLeg::Caller]
+                        .into_iter()
+                        .find(|leg| call.leg(leg).uri.equivalent(&uri))
+                        .ok_or_else(|| format!("{} is not a party of call {}", uri, call_id))?
+                }
+                None => CallLeg::Callee,
+            };
+            call.consultation = Some(consult_id.to_string());
+            (
+                call.leg(&transferor).uri.to_string(),
+                call.leg(&transferor.other()).uri.to_string(),
+            )
+        };
+
+        if let Err(e) = self.hold_call_from(call_id, &transferor).await {
+            if let Some(call) = self.active_calls.write().await.get_mut(call_id) {
+                call.consultation = None;
+            }
+            return Err(e);
+        }
+        info!("Call {} on hold while {} consults {}", call_id, transferor, consult_id);
+        Ok((transferor, transferee))
+    }
+
+    /// End the consultation of a call without splicing it in, taking the
+    /// transferee off hold
+    pub async fn end_consultation(&self, call_id: &str) -> Result<(), String> {
+        {
+            let mut calls = self.active_calls.write().await;
+            let call = calls
+                .get_mut(call_id)
+                .ok_or_else(|| format!("Call {} not found", call_id))?;
+            if call.consultation.take().is_none() {
+                return Err(format!("Call {} has no consultation", call_id));
+            }
+        }
+        if self.is_call_on_hold(call_id).await {
+            self.resume_call(call_id).await?;
+        }
+        Ok(())
+    }
+
+    /// Give the CDR of `other_id` the correlation id of the CDR of
+    /// `call_id`, so the two are reported as one call
+    pub async fn link_calls(&self, call_id: &str, other_id: &str) {
+        let Some(cdr_repo) = &self.cdr_repository else {
+            return;
+        };
+        let cdr_ids = {
+            let calls = self.active_calls.read().await;
+            calls
+                .get(call_id)
+                .map(|call| call.cdr_id)
+                .zip(calls.get(other_id).map(|call| call.cdr_id))
+        };
+        let Some((cdr_id, other_cdr_id)) = cdr_ids else {
+            return;
+        };
+
+        let mut cdr = match cdr_repo.get_by_id(cdr_id).await {
+            Ok(Some(cdr)) => cdr,
+            Ok(None) => return,
+            Err(e) => {
+                error!("Failed to load CDR of call {}: {}", call_id, e);
+                return;
+            }
+        };
+        let correlation_id = match &cdr.correlation_id {
+            Some(correlation_id) => correlation_id.clone(),
+            None => {
+                cdr.set_correlation_id(call_id.to_string());
+                if let Err(e) = cdr_repo.update(&cdr).await {
+                    error!("Failed to update CDR of call {}: {}", call_id, e);
+                }
+                call_id.to_string()
+            }
+        };
+        match cdr_repo.get_by_id(other_cdr_id).await {
+            Ok(Some(mut other)) => {
+                other.set_correlation_id(correlation_id);
+                if let Err(e) = cdr_repo.update(&other).await {
+                    error!("Failed to link CDR of call {}: {}", other_id, e);
+                }
+            }
+            Ok(None) => {}
+            Err(e) => error!("Failed to load CDR of call {}: {}", other_id, e),
+        }
+    }
+
+    /// Splice a consultation call into the call it consults for
+    ///
+    /// The transferor is the party the two calls share; the consulted party
+    /// takes their place in `call_id`, media and all, and the transferee
+    /// comes off hold. The consultation call is gone afterwards without a
+    /// BYE to the consulted party, whose requests on it now reach
+    /// `call_id`; its CDR ends as transferred, linked to the call's.
+    /// Returns the transferor's leg of `call_id`, for the caller to release.
+    pub async fn splice_consultation(
+        &self,
+        call_id: &str,
+        consult_id: &str,
+    ) -> Result<ReplacedLeg, String> {
+        let mut calls = self.active_calls.write().await;
+        match calls.get(call_id).map(|call| call.state().is_established()) {
+            Some(true) => {}
+            Some(false) => return Err("Call must be established to be transferred".to_string()),
+            None => return Err(format!("Call {} not found", call_id)),
+        }
+        match calls.get(consult_id).map(|call| call.state().is_established()) {
+            Some(true) => {}
+            Some(false) => return Err(format!("Consultation call {} is not answered", consult_id)),
+            None => return Err(format!("Consultation call {} not found", consult_id)),
+        }
+        let Some(mut consult) = calls.remove(consult_id) else {
+            return Err(format!("Consultation call {} not found", consult_id));
+        };
+        let Some(call) = calls.get_mut(call_id) else {
+            return Err(format!("Call {} not found", call_id));
+        };
+
+        // The transferor is on both calls; the consultation's other party
+        // is the target
+        let mut shared = None;
+        for consult_leg in [CallLeg::Caller, CallLeg::Callee] {
+            for leg in [CallLeg::Callee, CallLeg::Caller] {
+                if shared.is_none() && consult.leg(&consult_leg).uri.equivalent(&call.leg(&leg).uri) {
+                    shared = Some((consult_leg.clone(), leg));
+                }
+            }
+        }
+        let (consult_transferor, transferor) = shared.unwrap_or((CallLeg::Caller, CallLeg::Callee));
+        let target_leg = consult_transferor.other();
+        let side = |leg: &CallLeg| match leg {
+            CallLeg::Caller => BridgeLeg::A,
+            CallLeg::Callee => BridgeLeg::B,
+        };
+        let pick = |formats: (PacketFormat, PacketFormat), leg: &CallLeg| match leg {
+            CallLeg::Caller => formats.0,
+            CallLeg::Callee => formats.1,
+        };
+        let format = consult
+            .media_bridge
+            .as_ref()
+            .and_then(|bridge| bridge.packet_formats())
+            .map(|formats| pick(formats, &target_leg))
+            .or_else(|| {
+                call.media_bridge
+                    .as_ref()
+                    .and_then(|bridge| bridge.packet_formats())
+                    .map(|formats| pick(formats, &transferor))
+            });
+        let target = consult.leg_mut(&target_leg);
+        let target_uri = target.uri.clone();
+        let target_contact = target.contact;
+        let stream = target.media_stream.take();
+
+        call.consultation = None;
+        call.spliced_out = Some(call.leg(&transferor).uri.clone());
+        let leg = call.leg_mut(&transferor);
+        let replaced = ReplacedLeg {
+            uri: leg.uri.to_string(),
+            contact: leg.contact,
+            confirmed: true,
+        };
+        leg.uri = target_uri.clone();
+        leg.contact = target_contact;
+        let old_stream = std::mem::replace(&mut leg.media_stream, stream.clone());
+        let bridge = match (&call.media_bridge, stream, format) {
+            (Some(bridge), Some(stream), Some(format)) => {
+                Some(bridge.replace_leg(side(&transferor), stream, format).await.0)
+            }
+            _ => None,
+        };
+        if let Some(bridge) = bridge {
+            call.media_bridge = Some(Arc::new(bridge));
+        }
+        let cdr_ids = (call.cdr_id, consult.cdr_id);
+        let consult_bridge = consult.media_bridge.take();
+        drop(calls);
+
+        // The target's stream lives on in the call's bridge
+        if let Some(bridge) = consult_bridge {
+            bridge.retire().await;
+        }
+        if let Some(old) = old_stream {
+            old.close().await;
+        }
+        if self.is_call_on_hold(call_id).await {
+            if let Err(e) = self.resume_call(call_id).await {
+                warn!("Failed to resume transferee of call {}: {}", call_id, e);
+            }
+        }
+        self.report_call_ended(consult_id, &consult);
+        self.record_call_ended(consult_id, EndReason::NormalClearing).await;
+        self.release_call_resources(consult_id, consult).await;
+        self.aliases
+            .write()
+            .await
+            .insert(consult_id.to_string(), call_id.to_string());
+        self.record_splice(call_id, consult_id, cdr_ids, &transferor, &target_uri.to_string())
+            .await;
+
+        info!(
+            "Consultation {} spliced into call {}: {} replaced by {}",
+            consult_id, call_id, replaced.uri, target_uri
+        );
+        Ok(replaced)
+    }
+
+    /// End the CDR of a spliced consultation as transferred, linked to the
+    /// CDR of the call it was spliced into
+    async fn record_splice(
+        &self,
+        call_id: &str,
+        consult_id: &str,
+        (cdr_id, consult_cdr_id): (Uuid, Uuid),
+        transferor: &CallLeg,
+        target_uri: &str,
+    ) {
+        let Some(cdr_repo) = &self.cdr_repository else {
+            return;
+        };
+        let mut cdr = match cdr_repo.get_by_id(cdr_id).await {
+            Ok(Some(cdr)) => cdr,
+            Ok(None) => return,
+            Err(e) => {
+                error!("Failed to load CDR of transferred call {}: {}", call_id, e);
+                return;
+            }
+        };
+        let correlation_id = cdr
+            .correlation_id
+            .clone()
+            .unwrap_or_else(|| call_id.to_string());
+
+        match cdr_repo.get_by_id(consult_cdr_id).await {
+            Ok(Some(mut consult)) => {
+                consult.set_correlation_id(correlation_id.clone());
+                consult.mark_ended(
+                    CallStatus::Completed,
+                    Some(format!("Transferred into {}", call_id)),
+                    None,
+                );
+                if let Err(e) = cdr_repo.update(&consult).await {
+                    error!("Failed to update CDR of consultation {}: {}", consult_id, e);
+                }
+            }
+            Ok(None) => {}
+            Err(e) => error!("Failed to load CDR of consultation {}: {}", consult_id, e),
+        }
+
+        cdr.set_correlation_id(correlation_id);
+        if *transferor == CallLeg::Callee {
+            cdr.set_callee(target_uri.to_string());
+        }
+        if let Err(e) = cdr_repo.update(&cdr).await {
+            error!("Failed to update CDR after transfer: {}", e);
+        }
+    }
+
+    /// Stop relaying media between the legs of a call, e.g. once they are
+    /// mixed in a conference; the legs' streams stay open
+    pub async fn detach_media(&self, call_id: &str) -> bool {
+        let bridge = self
+            .active_calls
+            .write()
+            .await
+            .get_mut(call_id)
+            .and_then(|call| call.media_bridge.take());
+        match bridge {
+            Some(bridge) => {
+                bridge.retire().await;
+                true
+            }
+            None => false,
+        }
+    }
+
     /// Attended transfer (consultative transfer) - transfer after consultation
     ///
+    /// The consultation call named by the Replaces header is spliced into
+    /// the call; the transferor ends their dialogs themselves.
+    ///
     /// # Arguments
     /// * `call_id` - The original call to transfer
     /// * `target_uri` - The target URI (from Refer-To header)
     /// * `replaces` - The Replaces header value (identifies consultation call)
     ///
     /// # Returns
-    /// Ok(()) if transfer was initiated successfully
+    /// Ok(()) if the consultation was spliced into the call
     pub async fn attended_transfer(
         &self,
         call_id: &str,
@@ -777,26 +3113,222 @@ impl CallRouter {
         } else {
             return Err("Attended transfer requires Replaces header".to_string());
         };
+        let replaced_call_id = self.canonical_call_id(&replaced_call_id).await;
 
         debug!(
             "Attended transfer: replacing call {} with call {}",
             replaced_call_id, call_id
         );
+        self.splice_consultation(call_id, &replaced_call_id).await?;
+
+        info!("Attended transfer of call {} to {} completed", call_id, target_uri);
+        Ok(())
+    }
 
-        // In a real implementation, we would:
-        // 1. Verify the consultation call (replaced_call_id) exists
-        // 2. Send NOTIFY to transferor with "SIP/2.0 100 Trying"
-        // 3. Send INVITE to target with Replaces header
-        // 4. Wait for target to accept
-        // 5. Bridge the two calls
-        // 6. Send NOTIFY to transferor with success
-        // 7. Terminate transferor's legs
+    /// Let another device take over a leg of a call (INVITE/Replaces)
+    ///
+    /// The replaced leg is the callee's, or for an established call the
+    /// caller's when that one is the requester's. The requester must be the
+    /// leg's user, or be allowed by the takeover policy to take over that
+    /// user's calls. The new device's `media` takes the leg's place in the
+    /// bridge; the other party stays connected. Taking over a ringing call
+    /// answers it (a pickup). The replaced device is recorded in a CDR leg
+    /// of its own, linked to the call's CDR.
+    pub async fn take_over(
+        &self,
+        request: TakeoverRequest,
+        media: Option<(Arc<MediaStream>, PacketFormat)>,
+    ) -> Result<Takeover, TakeoverError> {
+        let replaces = &request.replaces;
+        let call_id = self.canonical_call_id(&replaces.call_id).await;
+        let requester_realm = realm_of(&request.requester_uri);
 
-        // For now, just mark as successful
-        // TODO: Implement actual attended transfer logic
+        let mut calls = self.active_calls.write().await;
+        let call = calls
+            .get_mut(&call_id)
+            .filter(|call| call.state().is_active())
+            .filter(|call| {
+                replaces.matches_tags(call.caller_tag.as_deref(), call.local_tag.as_deref())
+            })
+            .ok_or(TakeoverError::NoDialog)?;
 
-        info!("Attended transfer initiated for call {}", call_id);
-        Ok(())
+        let confirmed = call.state().is_established();
+        if replaces.early_only && confirmed {
+            return Err(TakeoverError::AlreadyAnswered);
+        }
+        let candidates: &[CallLeg] = if confirmed {
+            &[CallLeg::Callee, CallLeg::Caller]
+        } else {
+            &[CallLeg::Callee]
+        };
+        let same_tenant = |uri: &str| match (requester_realm, realm_of(uri)) {
+            (Some(ours), Some(theirs)) => ours.eq_ignore_ascii_case(theirs),
+            _ => true,
+        };
+        let owner = |leg: &CallLeg| Self::leg_user(&call.leg(leg).uri);
+        let leg = candidates
+            .iter()
+            .filter(|leg| same_tenant(&call.leg(leg).uri.to_string()))
+            .find(|leg| {
+                owner(leg) == request.requester_user
+                    && self.takeover_policy.permits(&request.requester_user, &owner(leg))
+            })
+            .or_else(|| {
+                candidates.iter().filter(|leg| same_tenant(&call.leg(leg).uri.to_string())).find(|leg| {
+                    self.takeover_policy.permits(&request.requester_user, &owner(leg))
+                })
+            })
+            .cloned()
+            .ok_or(TakeoverError::Forbidden)?;
+
+        let same_user = owner(&leg) == request.requester_user;
+        let info = call.leg_mut(&leg);
+        let replaced = ReplacedLeg {
+            uri: info.uri.to_string(),
+            contact: info.contact,
+            confirmed,
+        };
+        if !same_user {
+            info.retarget(&request.requester_uri);
+        }
+        info.contact = request.contact;
+
+        let bridge = match media {
+            Some((stream, format)) => {
+                let old_stream = info.media_stream.replace(stream.clone());
+                let bridge = match &call.media_bridge {
+                    Some(bridge) => {
+                        let side = match leg {
+                            CallLeg::Caller => BridgeLeg::A,
+                            CallLeg::Callee => BridgeLeg::B,
+                        };
+                        let (bridge, old) = bridge.replace_leg(side, stream, format).await;
+                        old.close().await;
+                        bridge
+                    }
+                    None => MediaBridge::new(stream.clone(), stream)
+                        .with_packet_formats(format, format),
+                };
+                if let Some(old) = old_stream {
+                    old.close().await;
+                }
+                let bridge = Arc::new(bridge);
+                call.media_bridge = Some(bridge.clone());
+                Some(bridge)
+            }
+            None => None,
+        };
+
+        if !confirmed {
+            if let Err(e) = call.process_event(CallEvent::Answer) {
+                warn!("Failed to answer picked-up call {}: {}", call_id, e);
+            }
+        }
+        let cdr_id = call.cdr_id;
+        drop(calls);
+
+        if !confirmed {
+            if let Some(events) = &self.call_events {
+                if let Err(e) = events.answer(&call_id).await {
+                    warn!("Failed to record answer of call {}: {}", call_id, e);
+                }
+            }
+        }
+        // The new device's offer/answer state is kept under its own Call-ID
+        self.aliases
+            .write()
+            .await
+            .insert(request.call_id.clone(), call_id.clone());
+        self.record_takeover(&call_id, cdr_id, &leg, &replaced, &request.requester_uri, same_user)
+            .await;
+
+        info!(
+            "Call {} {} by {} (replaced {})",
+            call_id,
+            if confirmed { "taken over" } else { "picked up" },
+            request.requester_uri,
+            replaced.uri
+        );
+        Ok(Takeover {
+            call_id,
+            replaced,
+            bridge,
+        })
+    }
+
+    /// Add a CDR leg for the device a takeover replaced
+    async fn record_takeover(
+        &self,
+        call_id: &str,
+        cdr_id: Uuid,
+        leg: &CallLeg,
+        replaced: &ReplacedLeg,
+        requester_uri: &str,
+        same_user: bool,
+    ) {
+        let Some(cdr_repo) = &self.cdr_repository else {
+            return;
+        };
+        let mut cdr = match cdr_repo.get_by_id(cdr_id).await {
+            Ok(Some(cdr)) => cdr,
+            Ok(None) => return,
+            Err(e) => {
+                error!("Failed to load CDR of taken-over call {}: {}", call_id, e);
+                return;
+            }
+        };
+        let correlation_id = cdr
+            .correlation_id
+            .clone()
+            .unwrap_or_else(|| call_id.to_string());
+
+        let replaced_ip = replaced.contact.as_ref().map(Self::socket_to_ip);
+        let mut record = match leg {
+            CallLeg::Callee => {
+                let mut record = CallDetailRecord::new(
+                    call_id.to_string(),
+                    cdr.caller_username.clone(),
+                    cdr.caller_uri.clone(),
+                    cdr.caller_ip.clone(),
+                    Self::extract_username(&replaced.uri),
+                    replaced.uri.clone(),
+                    cdr.direction,
+                );
+                if let Some(ip) = replaced_ip {
+                    record.set_callee_ip(ip);
+                }
+                record
+            }
+            CallLeg::Caller => CallDetailRecord::new(
+                call_id.to_string(),
+                Self::extract_username(&replaced.uri),
+                replaced.uri.clone(),
+                replaced_ip.unwrap_or_else(|| cdr.caller_ip.clone()),
+                cdr.callee_username.clone(),
+                cdr.callee_uri.clone(),
+                cdr.direction,
+            ),
+        };
+        record.start_time = cdr.start_time;
+        record.set_correlation_id(correlation_id.clone());
+        let status = if replaced.confirmed {
+            record.answer_time = cdr.answer_time;
+            CallStatus::Completed
+        } else {
+            CallStatus::Cancelled
+        };
+        record.mark_ended(status, Some(format!("Replaced by {}", requester_uri)), None);
+        if let Err(e) = cdr_repo.create(&record).await {
+            error!("Failed to create takeover CDR leg for {}: {}", call_id, e);
+        }
+
+        cdr.set_correlation_id(correlation_id);
+        if !same_user && *leg == CallLeg::Callee {
+            cdr.set_callee(requester_uri.to_string());
+        }
+        if let Err(e) = cdr_repo.update(&cdr).await {
+            error!("Failed to update CDR after takeover: {}", e);
+        }
     }
 
     /// Parse Replaces header
@@ -814,6 +3346,7 @@ impl CallRouter {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::infrastructure::media::TonePlan;
 
     #[tokio::test]
     async fn test_call_router_creation() {
@@ -863,6 +3396,21 @@ mod tests {
         assert_eq!(state, Some(CallState::Established));
     }
 
+    #[tokio::test]
+    async fn test_originate_call() {
+        let registrar = Arc::new(Registrar::new());
+        let router = CallRouter::new(registrar);
+
+        let call_id = router
+            .originate("sip:alice@example.com", "sip:bob@example.com")
+            .await
+            .unwrap();
+        assert_eq!(router.get_call_state(&call_id).await, Some(CallState::Ringing));
+
+        router.answer_call(&call_id).await.unwrap();
+        assert_eq!(router.get_call_state(&call_id).await, Some(CallState::Established));
+    }
+
     #[tokio::test]
     async fn test_terminate_call() {
         let registrar = Arc::new(Registrar::new());
@@ -1165,22 +3713,58 @@ mod tests {
         // Create and answer a call
         router
             .create_call(
-                "call-remote-hold".to_string(),
+                "call-remote-hold".to_string(),
+                "sip:alice@example.com".to_string(),
+                "sip:bob@example.com".to_string(),
+            )
+            .await
+            .unwrap();
+
+        router.answer_call("call-remote-hold").await.unwrap();
+
+        // Remote party puts us on hold
+        router.remote_hold("call-remote-hold").await.unwrap();
+        assert!(router.is_call_on_hold("call-remote-hold").await);
+
+        // Remote party resumes
+        router.remote_resume("call-remote-hold").await.unwrap();
+        assert!(!router.is_call_on_hold("call-remote-hold").await);
+    }
+
+    #[tokio::test]
+    async fn test_local_and_remote_holds_pause_talk_time() {
+        let router = CallRouter::new(Arc::new(Registrar::new()));
+        router
+            .create_call(
+                "call-hold-time".to_string(),
                 "sip:alice@example.com".to_string(),
                 "sip:bob@example.com".to_string(),
             )
             .await
             .unwrap();
+        router.answer_call("call-hold-time").await.unwrap();
+        let held = || async {
+            let calls = router.active_calls.read().await;
+            calls["call-hold-time"].state_machine.is_held()
+        };
 
-        router.answer_call("call-remote-hold").await.unwrap();
-
-        // Remote party puts us on hold
-        router.remote_hold("call-remote-hold").await.unwrap();
-        assert!(router.is_call_on_hold("call-remote-hold").await);
-
-        // Remote party resumes
-        router.remote_resume("call-remote-hold").await.unwrap();
-        assert!(!router.is_call_on_hold("call-remote-hold").await);
+        // Held by us, then by the remote party too: one segment, which our
+        // resume does not end while the remote party still holds
+        router.hold_call("call-hold-time").await.unwrap();
+        router.remote_hold("call-hold-time").await.unwrap();
+        router.resume_call("call-hold-time").await.unwrap();
+        assert!(held().await);
+        router.remote_resume("call-hold-time").await.unwrap();
+        assert!(!held().await);
+
+        // Held by the remote party alone
+        router.remote_hold("call-hold-time").await.unwrap();
+        assert!(held().await);
+        let info = router.get_active_call("call-hold-time").await.unwrap();
+        assert_eq!(info.hold_count, 2);
+        assert_eq!(info.talk_secs, 0);
+        assert_eq!(info.setup_secs, Some(0));
+        assert_eq!(info.ring_secs, None);
     }
 
     #[tokio::test]
@@ -1209,6 +3793,178 @@ mod tests {
         assert_eq!(router.get_call_state("call-moh-cleanup").await, None);
     }
 
+    #[tokio::test]
+    async fn test_held_calls_stream_tenant_moh() {
+        use crate::domain::audio::{
+            AudioCategory, AudioLibrary, AudioLibraryConfig, WavFile, WavFormat,
+        };
+        use crate::domain::tenant_branding::BrandingPatch;
+
+        let wav = |samples: usize| {
+            WavFile {
+                format: WavFormat {
+                    channels: 1,
+                    sample_rate: 8000,
+                    bits_per_sample: 16,
+                    audio_format: 1,
+                },
+                data: Arc::new(vec![0x10; samples * 2]),
+            }
+            .to_wav_bytes()
+        };
+        let library = Arc::new(AudioLibrary::new(AudioLibraryConfig {
+            root: std::env::temp_dir().join(format!("yakyak-moh-{}", Uuid::new_v4())),
+            ..Default::default()
+        }));
+        let branding = Arc::new(
+            BrandingRegistry::new("moh/default.wav".to_string()).with_audio_library(library.clone()),
+        );
+        let router = CallRouter::new(Arc::new(Registrar::new())).with_branding(branding.clone());
+
+        let mut moh_paths = Vec::new();
+        for (realm, samples) in [("acme.example.com", 800), ("globex.example.com", 1600)] {
+            let moh = library
+                .upload(Some(realm), "hold", AudioCategory::Moh, &wav(samples))
+                .unwrap();
+            branding
+                .update(
+                    realm,
+                    BrandingPatch {
+                        moh_file: Some(Some(moh.id)),
+                        ..Default::default()
+                    },
+                )
+                .unwrap();
+            moh_paths.push(moh.path.to_string_lossy().to_string());
+        }
+
+        for (call_id, caller) in [
+            ("call-acme", "sip:alice@acme.example.com"),
+            ("call-globex", "sip:bob@globex.example.com"),
+            ("call-other", "sip:carol@example.com"),
+        ] {
+            router
+                .create_call(call_id.to_string(), caller.to_string(), "sip:1000@pbx.local".to_string())
+                .await
+                .unwrap();
+            router.answer_call(call_id).await.unwrap();
+            router.hold_call(call_id).await.unwrap();
+        }
+
+        assert_eq!(router.moh_source("call-acme").await, Some(moh_paths[0].clone()));
+        assert_eq!(router.moh_source("call-globex").await, Some(moh_paths[1].clone()));
+        assert_ne!(moh_paths[0], moh_paths[1]);
+        // Calls of unbranded tenants get the global default
+        assert_eq!(
+            router.moh_source("call-other").await.as_deref(),
+            Some("moh/default.wav")
+        );
+
+        router.resume_call("call-acme").await.unwrap();
+        assert_eq!(router.moh_source("call-acme").await, None);
+    }
+
+    #[tokio::test]
+    async fn test_moh_class_selected_per_queue_and_tenant() {
+        use crate::domain::audio::{
+            AudioCategory, AudioLibrary, AudioLibraryConfig, AudioLibraryError, WavFile, WavFormat,
+        };
+        use crate::domain::tenant_branding::TenantBranding;
+        use crate::infrastructure::media::MohClassConfig;
+
+        // Distinct contents, so every upload is its own file
+        let wav = |fill: u8| {
+            WavFile {
+                format: WavFormat {
+                    channels: 1,
+                    sample_rate: 8000,
+                    bits_per_sample: 16,
+                    audio_format: 1,
+                },
+                data: Arc::new(vec![fill; 16000]),
+            }
+            .to_wav_bytes()
+        };
+        let library = Arc::new(AudioLibrary::new(AudioLibraryConfig {
+            root: std::env::temp_dir().join(format!("yakyak-moh-classes-{}", Uuid::new_v4())),
+            ..Default::default()
+        }));
+        let ids: Vec<String> = (1..=4)
+            .map(|fill| library.upload(None, "track", AudioCategory::Moh, &wav(fill)).unwrap().id)
+            .collect();
+        let classes = Arc::new(MohClassRegistry::new(library.clone()));
+        for (name, file) in [("sales", &ids[0]), ("support", &ids[1]), ("default", &ids[2])] {
+            classes
+                .load(
+                    name,
+                    MohClassConfig {
+                        files: vec![file.clone()],
+                        ..Default::default()
+                    },
+                )
+                .unwrap();
+        }
+        let branding = Arc::new(BrandingRegistry::new("moh/default.wav".to_string()));
+        branding.set_tenant_branding(
+            "acme.example.com",
+            TenantBranding {
+                moh_class: Some("support".to_string()),
+                ..Default::default()
+            },
+        );
+        let router = CallRouter::new(Arc::new(Registrar::new()))
+            .with_branding(branding)
+            .with_moh_classes(classes.clone());
+
+        for (call_id, caller, queue) in [
+            ("call-sales", "sip:alice@acme.example.com", Some("sales")),
+            ("call-acme", "sip:bob@acme.example.com", None),
+            ("call-other", "sip:carol@example.com", None),
+            ("call-unknown-queue", "sip:dave@example.com", Some("no-such-class")),
+        ] {
+            router
+                .create_call(call_id.to_string(), caller.to_string(), "sip:1000@pbx.local".to_string())
+                .await
+                .unwrap();
+            router.answer_call(call_id).await.unwrap();
+            router.set_moh_class(call_id, queue.map(str::to_string)).await.unwrap();
+            router.hold_call(call_id).await.unwrap();
+        }
+
+        let playing = |call: Option<ActiveCallInfo>| {
+            let moh = call.unwrap().moh.unwrap();
+            (moh.class, moh.file_id)
+        };
+        // The queue's class beats the tenant's, which beats the default
+        assert_eq!(playing(router.get_active_call("call-sales").await), ("sales".to_string(), ids[0].clone()));
+        assert_eq!(playing(router.get_active_call("call-acme").await), ("support".to_string(), ids[1].clone()));
+        assert_eq!(playing(router.get_active_call("call-other").await), ("default".to_string(), ids[2].clone()));
+        assert_eq!(
+            playing(router.get_active_call("call-unknown-queue").await),
+            ("default".to_string(), ids[2].clone())
+        );
+
+        // Files of a class cannot be deleted
+        assert!(matches!(library.delete(None, &ids[0]), Err(AudioLibraryError::InUse(_))));
+
+        // A reload leaves held calls on the old playlist
+        classes
+            .load(
+                "sales",
+                MohClassConfig {
+                    files: vec![ids[3].clone()],
+                    ..Default::default()
+                },
+            )
+            .unwrap();
+        assert_eq!(playing(router.get_active_call("call-sales").await).1, ids[0]);
+        router.resume_call("call-sales").await.unwrap();
+        assert!(router.get_active_call("call-sales").await.unwrap().moh.is_none());
+        router.hold_call("call-sales").await.unwrap();
+        assert_eq!(playing(router.get_active_call("call-sales").await).1, ids[3]);
+        assert!(library.delete(None, &ids[0]).is_ok());
+    }
+
     #[tokio::test]
     async fn test_blind_transfer() {
         let registrar = Arc::new(Registrar::new());
@@ -1273,6 +4029,273 @@ mod tests {
         assert!(result.unwrap_err().contains("not found"));
     }
 
+    /// Scripted final status per target, recording every INVITE
+    struct ScriptedTargets {
+        statuses: HashMap<&'static str, u16>,
+        invited: std::sync::Mutex<Vec<String>>,
+    }
+
+    #[async_trait::async_trait]
+    impl InviteForwarder for ScriptedTargets {
+        async fn forward(&self, target: &str, request: &SipRequest) -> Result<SipResponse, SipError> {
+            self.invited.lock().unwrap().push(target.to_string());
+            let status = self.statuses.get(target).copied().unwrap_or(480);
+            ResponseBuilder::new(status).build_for_request(request)
+        }
+    }
+
+    #[derive(Default)]
+    struct RecordingNotifier {
+        sent: std::sync::Mutex<Vec<(String, bool)>>,
+        /// Subscription-State of each NOTIFY
+        states: std::sync::Mutex<Vec<String>>,
+    }
+
+    #[async_trait::async_trait]
+    impl TransferNotifier for RecordingNotifier {
+        async fn notify(
+            &self,
+            _call_id: &str,
+            transferor_uri: &str,
+            notify: &ReferNotify,
+        ) -> Result<(), SipError> {
+            assert_eq!(transferor_uri, "sip:bob@example.com");
+            self.sent
+                .lock()
+                .unwrap()
+                .push((notify.sipfrag.clone(), notify.is_terminated()));
+            self.states
+                .lock()
+                .unwrap()
+                .push(notify.subscription_state.clone());
+            Ok(())
+        }
+    }
+
+    async fn transferring_call(router: &CallRouter, call_id: &str) {
+        router
+            .create_call(
+                call_id.to_string(),
+                "sip:alice@example.com".to_string(),
+                "sip:bob@example.com".to_string(),
+            )
+            .await
+            .unwrap();
+        router.answer_call(call_id).await.unwrap();
+        router
+            .blind_transfer_from(call_id, "sip:bob@example.com", "sip:charlie@example.com")
+            .await
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_busy_transfer_target_reconnects_transferor() {
+        let router = CallRouter::new(Arc::new(Registrar::new()));
+        transferring_call(&router, "call-xfer-busy").await;
+
+        // Alice waits on MOH with the transfer recorded on the call
+        assert!(router.is_call_on_hold("call-xfer-busy").await);
+        let pending = router.pending_transfer("call-xfer-busy").await.unwrap();
+        assert_eq!(pending.transferor, CallLeg::Callee);
+        assert_eq!(pending.recovery_target, "sip:bob@example.com");
+        assert!(!pending.is_expired());
+
+        let targets = ScriptedTargets {
+            statuses: HashMap::from([("sip:charlie@example.com", 486), ("sip:bob@example.com", 200)]),
+            invited: Default::default(),
+        };
+        let notifier = RecordingNotifier::default();
+        let outcome = router
+            .execute_transfer("call-xfer-busy", &targets, &notifier)
+            .await
+            .unwrap();
+
+        assert_eq!(
+            outcome,
+            TransferOutcome::Recovered {
+                target: "sip:bob@example.com".to_string(),
+                failed_status: 486,
+            }
+        );
+        assert_eq!(
+            *targets.invited.lock().unwrap(),
+            vec!["sip:charlie@example.com", "sip:bob@example.com"]
+        );
+        // Bob learned of the failure before being rung back
+        assert_eq!(
+            *notifier.sent.lock().unwrap(),
+            vec![
+                ("SIP/2.0 100 Trying".to_string(), false),
+                ("SIP/2.0 486 Busy Here".to_string(), true),
+            ]
+        );
+
+        let call = router.get_active_call("call-xfer-busy").await.unwrap();
+        assert_eq!(call.callee_uri, "sip:bob@example.com");
+        assert!(!call.on_hold);
+        assert!(router.pending_transfer("call-xfer-busy").await.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_failed_transfer_recovers_after_transferor_hung_up() {
+        let router = CallRouter::new(Arc::new(Registrar::new())).with_transfer_policy(TransferPolicy {
+            fallback_uri: Some("sip:vm-{user}@example.com".to_string()),
+            ..Default::default()
+        });
+        transferring_call(&router, "call-xfer-gone").await;
+
+        // Bob's BYE leaves alice waiting for the transfer
+        assert!(router.release_transferor("call-xfer-gone", "sip:bob@example.com").await);
+        assert!(!router.release_transferor("call-xfer-gone", "sip:alice@example.com").await);
+        assert!(router.get_call_state("call-xfer-gone").await.is_some());
+
+        let targets = ScriptedTargets {
+            statuses: HashMap::from([("sip:charlie@example.com", 486), ("sip:vm-bob@example.com", 200)]),
+            invited: Default::default(),
+        };
+        let notifier = RecordingNotifier::default();
+        let outcome = router
+            .execute_transfer("call-xfer-gone", &targets, &notifier)
+            .await
+            .unwrap();
+
+        assert_eq!(
+            outcome,
+            TransferOutcome::Recovered {
+                target: "sip:vm-bob@example.com".to_string(),
+                failed_status: 486,
+            }
+        );
+        assert!(notifier.sent.lock().unwrap().is_empty());
+        let call = router.get_active_call("call-xfer-gone").await.unwrap();
+        assert_eq!(call.callee_uri, "sip:vm-bob@example.com");
+    }
+
+    /// Target that rings (180) and then answers busy (486), after `delay`
+    struct RingingThenBusy {
+        delay: std::time::Duration,
+    }
+
+    #[async_trait::async_trait]
+    impl InviteForwarder for RingingThenBusy {
+        async fn forward(&self, target: &str, request: &SipRequest) -> Result<SipResponse, SipError> {
+            self.forward_with_progress(target, request, mpsc::unbounded_channel().0)
+                .await
+        }
+
+        async fn forward_with_progress(
+            &self,
+            target: &str,
+            request: &SipRequest,
+            progress: mpsc::UnboundedSender<u16>,
+        ) -> Result<SipResponse, SipError> {
+            if target == "sip:bob@example.com" {
+                return ResponseBuilder::new(200).build_for_request(request);
+            }
+            let _ = progress.send(180);
+            let _ = progress.send(180);
+            tokio::time::sleep(self.delay).await;
+            ResponseBuilder::new(486).build_for_request(request)
+        }
+    }
+
+    #[tokio::test]
+    async fn test_transfer_progress_notifies_ringing_then_busy() {
+        let router = CallRouter::new(Arc::new(Registrar::new()));
+        transferring_call(&router, "call-xfer-ringing").await;
+
+        let notifier = RingingThenBusy {
+            delay: std::time::Duration::from_millis(20),
+        };
+        let recorder = RecordingNotifier::default();
+        router
+            .execute_transfer("call-xfer-ringing", &notifier, &recorder)
+            .await
+            .unwrap();
+
+        // The repeated 180 is reported once; the final status ends the subscription
+        assert_eq!(
+            *recorder.sent.lock().unwrap(),
+            vec![
+                ("SIP/2.0 100 Trying".to_string(), false),
+                ("SIP/2.0 180 Ringing".to_string(), false),
+                ("SIP/2.0 486 Busy Here".to_string(), true),
+            ]
+        );
+        assert_eq!(
+            recorder.states.lock().unwrap()[2],
+            "terminated;reason=noresource"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_declined_refer_subscription_sends_no_notify() {
+        let router = CallRouter::new(Arc::new(Registrar::new()));
+        transferring_call(&router, "call-xfer-norefersub").await;
+        assert!(router.decline_refer_subscription("call-xfer-norefersub").await);
+        assert!(router
+            .refresh_refer_subscription("call-xfer-norefersub", std::time::Duration::from_secs(60))
+            .await
+            .is_err());
+
+        let targets = ScriptedTargets {
+            statuses: HashMap::from([("sip:charlie@example.com", 486), ("sip:bob@example.com", 200)]),
+            invited: Default::default(),
+        };
+        let notifier = RecordingNotifier::default();
+        let outcome = router
+            .execute_transfer("call-xfer-norefersub", &targets, &notifier)
+            .await
+            .unwrap();
+
+        // The transfer itself is unaffected
+        assert_eq!(
+            outcome,
+            TransferOutcome::Recovered {
+                target: "sip:bob@example.com".to_string(),
+                failed_status: 486,
+            }
+        );
+        assert!(notifier.sent.lock().unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_refer_subscription_expiring_mid_transfer() {
+        let router = CallRouter::new(Arc::new(Registrar::new()));
+        transferring_call(&router, "call-xfer-expiry").await;
+        let (_, refreshed) = router
+            .refresh_refer_subscription("call-xfer-expiry", std::time::Duration::from_millis(50))
+            .await
+            .unwrap();
+        assert!(!refreshed.is_terminated());
+
+        // The subscription runs out while the target rings
+        let target = RingingThenBusy {
+            delay: std::time::Duration::from_millis(100),
+        };
+        let notifier = RecordingNotifier::default();
+        let outcome = router
+            .execute_transfer("call-xfer-expiry", &target, &notifier)
+            .await
+            .unwrap();
+
+        assert_eq!(
+            *notifier.states.lock().unwrap().last().unwrap(),
+            "terminated;reason=timeout"
+        );
+        assert_eq!(
+            notifier.sent.lock().unwrap().last().unwrap(),
+            &("SIP/2.0 486 Busy Here".to_string(), true)
+        );
+        assert_eq!(
+            outcome,
+            TransferOutcome::Recovered {
+                target: "sip:bob@example.com".to_string(),
+                failed_status: 486,
+            }
+        );
+    }
+
     #[tokio::test]
     async fn test_attended_transfer() {
         let registrar = Arc::new(Registrar::new());
@@ -1309,6 +4332,14 @@ mod tests {
             .await;
 
         assert!(result.is_ok());
+        // Charlie took bob's place; bob's BYE leaves the call up
+        let call = router.get_active_call("call-original").await.unwrap();
+        assert_eq!(call.caller_uri, "sip:alice@example.com");
+        assert_eq!(call.callee_uri, "sip:charlie@example.com");
+        assert!(router.get_active_call("call-consult").await.is_none());
+        assert_eq!(router.canonical_call_id("call-consult").await, "call-original");
+        assert!(router.release_transferor("call-original", "sip:bob@example.com").await);
+        assert!(!router.release_transferor("call-original", "sip:alice@example.com").await);
     }
 
     #[tokio::test]
@@ -1348,7 +4379,554 @@ mod tests {
         assert_eq!(call_id_simple, "simple-call-id");
     }
 
+    #[tokio::test]
+    async fn test_forward_with_redirects_records_hops() {
+        use crate::domain::cdr::MockCdrRepository;
+        use async_trait::async_trait;
+        use std::sync::Mutex;
+
+        /// 302 from the service, first contact unreachable, second answers
+        struct RedirectingUas;
+
+        #[async_trait]
+        impl InviteForwarder for RedirectingUas {
+            async fn forward(
+                &self,
+                target: &str,
+                request: &SipRequest,
+            ) -> Result<SipResponse, SipError> {
+                let extra = match target {
+                    "sip:svc@example.com" => "302 Moved Temporarily\r\nContact: <sip:bob@10.0.0.1>, <sip:bob@10.0.0.2>;q=0.5",
+                    "sip:bob@10.0.0.2" => "200 OK",
+                    _ => return Err(SipError::TransportError("unreachable".to_string())),
+                };
+                let (status_line, contact) = extra.split_once("\r\n").unwrap_or((extra, ""));
+                let code: u16 = status_line[..3].parse().unwrap();
+                let response = ResponseBuilder::new(code).build_for_request(request)?;
+                if contact.is_empty() {
+                    return Ok(response);
+                }
+                let text = String::from_utf8_lossy(&response.to_bytes())
+                    .replacen("\r\n", &format!("\r\n{}\r\n", contact), 1);
+                SipResponse::parse(text.as_bytes())
+            }
+        }
+
+        let stored: Arc<Mutex<Option<CallDetailRecord>>> = Arc::new(Mutex::new(None));
+        let legs: Arc<Mutex<Vec<CallDetailRecord>>> = Arc::new(Mutex::new(Vec::new()));
+        let mut mock = MockCdrRepository::new();
+        {
+            let stored = stored.clone();
+            let legs = legs.clone();
+            mock.expect_create().returning(move |cdr| {
+                let mut stored = stored.lock().unwrap();
+                if stored.is_none() {
+                    *stored = Some(cdr.clone());
+                } else {
+                    legs.lock().unwrap().push(cdr.clone());
+                }
+                Ok(())
+            });
+        }
+        {
+            let stored = stored.clone();
+            mock.expect_get_by_id()
+                .returning(move |_| Ok(stored.lock().unwrap().clone()));
+        }
+        {
+            let stored = stored.clone();
+            mock.expect_update().returning(move |cdr| {
+                *stored.lock().unwrap() = Some(cdr.clone());
+                Ok(())
+            });
+        }
+
+        let router = CallRouter::new(Arc::new(Registrar::new())).with_cdr_repository(Arc::new(mock));
+        router
+            .create_call(
+                "call-redirect".to_string(),
+                "sip:alice@example.com".to_string(),
+                "sip:svc@example.com".to_string(),
+            )
+            .await
+            .unwrap();
+
+        let request = SipRequest::parse(
+            b"INVITE sip:svc@example.com SIP/2.0\r\nVia: SIP/2.0/UDP 192.0.2.1;branch=z9hG4bKr\r\n\
+              Max-Forwards: 70\r\nFrom: <sip:alice@example.com>;tag=a\r\nTo: <sip:svc@example.com>\r\n\
+              Call-ID: call-redirect\r\nCSeq: 1 INVITE\r\nContent-Length: 0\r\n\r\n",
+        )
+        .unwrap();
+        let response = router
+            .forward_with_redirects(
+                "call-redirect",
+                &request,
+                "sip:svc@example.com",
+                TrustZone::Internal,
+                &RedirectingUas,
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status_code(), 200);
+
+        let cdr = stored.lock().unwrap().clone().unwrap();
+        assert_eq!(cdr.callee_uri, "sip:bob@10.0.0.2");
+        assert_eq!(cdr.redirect_count, 1);
+        assert_eq!(cdr.correlation_id.as_deref(), Some("call-redirect"));
+
+        // The redirecting service and the unreachable contact
+        let legs = legs.lock().unwrap();
+        assert_eq!(legs.len(), 2);
+        assert_eq!(legs[0].sip_response_code, Some(302));
+        assert_eq!(legs[1].callee_uri, "sip:bob@10.0.0.1");
+        assert!(legs.iter().all(|l| l.correlation_id == cdr.correlation_id));
+    }
+
+    #[tokio::test]
+    async fn test_forward_applies_lnp_routing_number() {
+        use crate::domain::routing::{RoutingLookup, RoutingOverride};
+        use crate::domain::shared::NumberingPlan;
+        use crate::infrastructure::lnp::LnpConfig;
+        use async_trait::async_trait;
+        use std::sync::Mutex;
+
+        struct Ported;
+
+        #[async_trait]
+        impl RoutingLookup for Ported {
+            async fn lookup(&self, number: &str) -> Result<RoutingOverride, String> {
+                Ok(RoutingOverride {
+                    routing_number: (number == "+14155551234").then(|| "+14155550000".to_string()),
+                    trunk: None,
+                })
+            }
+        }
+
+        /// Answers 200, remembering where it was sent
+        struct Recording(Mutex<Vec<String>>);
+
+        #[async_trait]
+        impl InviteForwarder for Recording {
+            async fn forward(
+                &self,
+                target: &str,
+                request: &SipRequest,
+            ) -> Result<SipResponse, SipError> {
+                self.0.lock().unwrap().push(target.to_string());
+                ResponseBuilder::new(200).build_for_request(request)
+            }
+        }
+
+        let lnp = LnpResolver::new(&LnpConfig::default(), Arc::new(Ported), NumberingPlan::default());
+        let router = CallRouter::new(Arc::new(Registrar::new())).with_lnp(Arc::new(lnp));
+        let forwarder = Recording(Mutex::new(Vec::new()));
+        for dialed in ["+14155551234", "+14155559999"] {
+            let request = SipRequest::parse(
+                format!(
+                    "INVITE sip:{dialed}@example.com SIP/2.0\r\nVia: SIP/2.0/UDP 192.0.2.1;branch=z9hG4bKl\r\n\
+                     Max-Forwards: 70\r\nFrom: <sip:alice@example.com>;tag=a\r\nTo: <sip:{dialed}@example.com>\r\n\
+                     Call-ID: call-lnp\r\nCSeq: 1 INVITE\r\nContent-Length: 0\r\n\r\n"
+                )
+                .as_bytes(),
+            )
+            .unwrap();
+            let target = format!("sip:{}@example.com", dialed);
+            router
+                .forward_with_redirects("call-lnp", &request, &target, TrustZone::Internal, &forwarder)
+                .await
+                .unwrap();
+        }
+
+        let targets = forwarder.0.lock().unwrap();
+        assert_eq!(targets[0], "sip:+14155551234;rn=+14155550000;npdi@example.com");
+        assert_eq!(targets[1], "sip:+14155559999@example.com");
+        assert_eq!(
+            with_routing_number("sip:+14155551234;npdi@example.com", "+14155550000"),
+            "sip:+14155551234;npdi@example.com"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_trunk_reservation_keeps_inbound_channel_and_fails_over() {
+        use super::super::header_rules::{HeaderRulesConfig, TrunkHeaderRules};
+        use super::super::trunk_manager::{TrunkChannelConfig, TrunkChannelLimits};
+        use async_trait::async_trait;
+        use std::sync::Mutex;
+
+        /// Answers 200, remembering where it was sent
+        struct Recording(Mutex<Vec<String>>);
+
+        #[async_trait]
+        impl InviteForwarder for Recording {
+            async fn forward(
+                &self,
+                target: &str,
+                request: &SipRequest,
+            ) -> Result<SipResponse, SipError> {
+                self.0.lock().unwrap().push(target.to_string());
+                ResponseBuilder::new(200).build_for_request(request)
+            }
+        }
+
+        let mut rules = HeaderRulesConfig::default();
+        let mut channels = TrunkChannelConfig::default();
+        for (trunk, host, total, reserved_inbound) in [
+            ("carrier-a", "a.example.com", 3, 1),
+            ("carrier-b", "b.example.com", 1, 0),
+        ] {
+            rules.trunks.insert(
+                trunk.to_string(),
+                TrunkHeaderRules {
+                    hosts: vec![host.to_string()],
+                    ..Default::default()
+                },
+            );
+            channels.trunks.insert(
+                trunk.to_string(),
+                TrunkChannelLimits {
+                    total,
+                    reserved_inbound,
+                    reserved_outbound: 0,
+                    host: Some(host.to_string()),
+                },
+            );
+        }
+        channels.failover = vec!["carrier-a".to_string(), "carrier-b".to_string()];
+        let trunks = Arc::new(TrunkManager::new(channels));
+        let router = CallRouter::new(Arc::new(Registrar::new()))
+            .with_header_rules(Arc::new(HeaderRulesEngine::new(rules).unwrap()))
+            .with_trunk_manager(trunks.clone());
+        let forwarder = Recording(Mutex::new(Vec::new()));
+
+        let dial = |call_id: &'static str| {
+            let router = &router;
+            let forwarder = &forwarder;
+            async move {
+                let target = "sip:+15550100@a.example.com";
+                router
+                    .create_call(call_id.to_string(), "sip:alice@example.com".to_string(), target.to_string())
+                    .await
+                    .unwrap();
+                let request = SipRequest::parse(
+                    format!(
+                        "INVITE {target} SIP/2.0\r\nVia: SIP/2.0/UDP 192.0.2.1;branch=z9hG4bK{call_id}\r\n\
+                         Max-Forwards: 70\r\nFrom: <sip:alice@example.com>;tag=a\r\nTo: <{target}>\r\n\
+                         Call-ID: {call_id}\r\nCSeq: 1 INVITE\r\nContent-Length: 0\r\n\r\n"
+                    )
+                    .as_bytes(),
+                )
+                .unwrap();
+                router
+                    .forward_with_redirects(call_id, &request, target, TrustZone::Internal, forwarder)
+                    .await
+                    .unwrap()
+                    .status_code()
+            }
+        };
+
+        // Outbound calls use up carrier-a's unreserved channels
+        assert_eq!(dial("out-1").await, 200);
+        assert_eq!(dial("out-2").await, 200);
+
+        // An inbound call still gets the reserved channel
+        router
+            .create_call("in-1".to_string(), "sip:+15550199@a.example.com".to_string(), "sip:alice@example.com".to_string())
+            .await
+            .unwrap();
+        assert!(router.seize_inbound_trunk("in-1", "carrier-a").await.is_ok());
+
+        // The next outbound call fails over to carrier-b, then there is no
+        // channel left for one more
+        assert_eq!(dial("out-3").await, 200);
+        assert_eq!(dial("out-4").await, 503);
+        assert_eq!(
+            *forwarder.0.lock().unwrap(),
+            vec![
+                "sip:+15550100@a.example.com",
+                "sip:+15550100@a.example.com",
+                "sip:+15550100@b.example.com",
+            ]
+        );
+        let occupancy = trunks.occupancy();
+        assert_eq!((occupancy[0].inbound, occupancy[0].outbound), (1, 2));
+        assert_eq!((occupancy[1].inbound, occupancy[1].outbound), (0, 1));
+
+        // Channels come back however the calls end
+        assert!(router.discard_call("out-4").await);
+        router.end_call("out-1", EndReason::NormalClearing).await.unwrap();
+        assert_eq!(dial("out-5").await, 200);
+        assert_eq!(forwarder.0.lock().unwrap().last().unwrap(), "sip:+15550100@a.example.com");
+    }
+
+    async fn alerting_call(router: &CallRouter, call_id: &str) {
+        router
+            .create_call(
+                call_id.to_string(),
+                "sip:alice@example.com".to_string(),
+                "sip:bob@example.com".to_string(),
+            )
+            .await
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_local_ringback_switches_to_early_media_mid_ring() {
+        let router = CallRouter::new(Arc::new(Registrar::new()));
+        alerting_call(&router, "call-ringback").await;
+        let caller = Arc::new(MediaStream::new(10130, 0, 8000).await.unwrap());
+        router
+            .set_leg_stream("call-ringback", &CallLeg::Caller, caller.clone())
+            .await;
+
+        // The callee only sends 180: the caller hears our ringback
+        let source = router
+            .callee_progress("call-ringback", 180, None, None)
+            .await
+            .unwrap();
+        assert_eq!(source, RingbackSource::LocalTone(TonePlan::Us));
+        assert_eq!(source.caller_status(180), 183);
+        let player = router.ringback_player("call-ringback").await.unwrap();
+        assert!(player.is_playing());
+
+        // Then its announcement starts: it replaces the tone
+        let early = Arc::new(MediaStream::new(10140, 0, 8000).await.unwrap());
+        let source = router
+            .callee_progress("call-ringback", 183, Some(early.clone()), None)
+            .await
+            .unwrap();
+        assert_eq!(source, RingbackSource::EarlyMedia);
+        assert!(player.is_closed());
+        assert!(router.ringback_player("call-ringback").await.is_none());
+        {
+            let calls = router.active_calls.read().await;
+            let call = calls.get("call-ringback").unwrap();
+            assert!(call.media_bridge.is_some());
+            assert!(Arc::ptr_eq(call.callee.media_stream.as_ref().unwrap(), &early));
+        }
+
+        // A repeated 180 does not bring the tone back
+        let source = router
+            .callee_progress("call-ringback", 180, None, None)
+            .await
+            .unwrap();
+        assert_eq!(source, RingbackSource::EarlyMedia);
+
+        router.answer_call("call-ringback").await.unwrap();
+        assert_eq!(router.ringback_source("call-ringback").await, None);
+        router.terminate_call("call-ringback").await.unwrap();
+        assert!(caller.is_closed());
+        assert!(early.is_closed());
+    }
+
+    #[tokio::test]
+    async fn test_ringback_policy_per_trunk_and_tenant() {
+        use crate::infrastructure::media::{RingbackPolicy, RingbackSettings, TonePlan};
+
+        let mut config = RingbackConfig::default();
+        config.tenants.insert(
+            "example.com".to_string(),
+            RingbackSettings {
+                policy: None,
+                tone_plan: Some(TonePlan::Uk),
+            },
+        );
+        for (trunk, policy) in [
+            ("no-early-media", RingbackPolicy::AlwaysLocal),
+            ("phone-rings", RingbackPolicy::NeverLocal),
+        ] {
+            config.trunks.insert(
+                trunk.to_string(),
+                RingbackSettings {
+                    policy: Some(policy),
+                    tone_plan: None,
+                },
+            );
+        }
+        let router = CallRouter::new(Arc::new(Registrar::new())).with_ringback(config);
+        for call_id in ["call-remote", "call-local", "call-silent"] {
+            alerting_call(&router, call_id).await;
+        }
+
+        // A callee with early media from the start is relayed
+        let early = Arc::new(MediaStream::new(10150, 0, 8000).await.unwrap());
+        let source = router
+            .callee_progress("call-remote", 183, Some(early), None)
+            .await
+            .unwrap();
+        assert_eq!(source, RingbackSource::EarlyMedia);
+        assert!(router.ringback_player("call-remote").await.is_none());
+
+        // A trunk always getting local ringback ignores its early media;
+        // the tenant's UK cadence plays
+        let ignored = Arc::new(MediaStream::new(10160, 0, 8000).await.unwrap());
+        let source = router
+            .callee_progress("call-local", 183, Some(ignored.clone()), Some("no-early-media"))
+            .await
+            .unwrap();
+        assert_eq!(source, RingbackSource::LocalTone(TonePlan::Uk));
+        let player = router.ringback_player("call-local").await.unwrap();
+        assert_eq!(player.plan(), TonePlan::Uk);
+        ignored.close().await;
+
+        // A trunk never getting local ringback leaves it to the phone
+        let source = router
+            .callee_progress("call-silent", 180, None, Some("phone-rings"))
+            .await
+            .unwrap();
+        assert_eq!(source, RingbackSource::Silence);
+        assert_eq!(source.caller_status(180), 180);
+
+        for call_id in ["call-remote", "call-local", "call-silent"] {
+            router.discard_call(call_id).await;
+        }
+        assert!(player.is_closed());
+    }
+
+    #[tokio::test]
+    async fn test_agent_bye_leaves_surveyed_caller_up() {
+        let router = CallRouter::new(Arc::new(Registrar::new()));
+        router
+            .create_call(
+                "call-survey".to_string(),
+                "sip:+15559999@trunk.example.net".to_string(),
+                "sip:agent1@example.com".to_string(),
+            )
+            .await
+            .unwrap();
+        // Not answered yet: nothing to survey
+        assert!(router.arm_survey("call-survey").await.is_err());
+        router.answer_call("call-survey").await.unwrap();
+
+        // Unarmed, the agent's BYE ends the call as usual
+        assert!(!router.release_agent("call-survey", "sip:agent1@example.com").await);
+
+        router.arm_survey("call-survey").await.unwrap();
+        // Only the agent's BYE is held back
+        assert!(!router.release_agent("call-survey", "sip:+15559999@trunk.example.net").await);
+        assert!(router.release_agent("call-survey", "sip:agent1@example.com").await);
+        let call = router.get_active_call("call-survey").await.unwrap();
+        assert_eq!(call.state, "Established");
+        assert_eq!(call.callee_contact, None);
+
+        // Once released, a further BYE ends the call
+        assert!(!router.release_agent("call-survey", "sip:agent1@example.com").await);
+        router.terminate_call("call-survey").await.unwrap();
+        assert!(router.get_active_call("call-survey").await.is_none());
+    }
+
     // Helper function to create a test request
+    /// Delivers our re-INVITEs to the peer's dialog layer, the first one
+    /// crossing the peer's own on the wire
+    struct PeerSender {
+        peer: Arc<DialogManager>,
+        peer_ip: &'static str,
+        together: Arc<tokio::sync::Barrier>,
+        sent: std::sync::atomic::AtomicU32,
+    }
+
+    #[async_trait::async_trait]
+    impl ReinviteSender for PeerSender {
+        async fn send_reinvite(
+            &self,
+            call_id: &str,
+            _leg: &CallLeg,
+            cseq: u32,
+            sdp: &str,
+        ) -> Result<(u16, Option<String>), SipError> {
+            use super::super::dialog::ReinviteAction;
+            use std::sync::atomic::Ordering;
+
+            let first = self.sent.fetch_add(1, Ordering::SeqCst) == 0;
+            if first {
+                self.together.wait().await;
+            }
+            let action = self.peer.get(call_id).await.unwrap().classify_reinvite(cseq);
+            if first {
+                // Neither side sees the other's 491 before its own INVITE arrives
+                self.together.wait().await;
+            }
+            let answer = match action {
+                ReinviteAction::Glare => return Ok((491, None)),
+                ReinviteAction::Retransmission(answer) => answer,
+                ReinviteAction::NewOffer => {
+                    let local = local_sdp(self.peer_ip);
+                    self.peer
+                        .with_dialog(call_id, false, |d| d.answer_offer(cseq, sdp, local))
+                        .await
+                }
+            };
+            Ok((200, Some(answer)))
+        }
+    }
+
+    fn local_sdp(ip: &str) -> super::super::sdp::SdpSession {
+        super::super::sdp::SdpSession::create_audio_session(ip.parse().unwrap(), 20000)
+    }
+
+    #[tokio::test]
+    async fn test_simultaneous_hold_reinvites_converge() {
+        use crate::infrastructure::media::StreamDirection;
+        use std::sync::atomic::Ordering;
+
+        tokio::time::pause();
+        let call_id = "call-hold-glare";
+        let together = Arc::new(tokio::sync::Barrier::new(2));
+        let dialogs_a = Arc::new(DialogManager::new());
+        let dialogs_b = Arc::new(DialogManager::new());
+        let to_b = Arc::new(PeerSender {
+            peer: dialogs_b.clone(),
+            peer_ip: "10.0.0.2",
+            together: together.clone(),
+            sent: Default::default(),
+        });
+        let to_a = Arc::new(PeerSender {
+            peer: dialogs_a.clone(),
+            peer_ip: "10.0.0.1",
+            together,
+            sent: Default::default(),
+        });
+        let mut a = CallRouter::new(Arc::new(Registrar::new())).with_reinvite_sender(to_b.clone());
+        a.dialog_manager = dialogs_a.clone();
+        let mut b = CallRouter::new(Arc::new(Registrar::new())).with_reinvite_sender(to_a.clone());
+        b.dialog_manager = dialogs_b.clone();
+
+        // A placed the call; its INVITE's offer was answered by B
+        for router in [&a, &b] {
+            router
+                .create_call(
+                    call_id.to_string(),
+                    "sip:alice@example.com".to_string(),
+                    "sip:bob@example.com".to_string(),
+                )
+                .await
+                .unwrap();
+            router.answer_call(call_id).await.unwrap();
+        }
+        let (cseq, offer) = dialogs_a
+            .with_dialog(call_id, true, |d| d.start_reinvite(local_sdp("10.0.0.1")))
+            .await
+            .unwrap();
+        let answer = dialogs_b
+            .with_dialog(call_id, false, |d| d.answer_offer(cseq, &offer, local_sdp("10.0.0.2")))
+            .await;
+        dialogs_a
+            .with_dialog(call_id, true, |d| d.on_reinvite_response(cseq, 200, Some(&answer)))
+            .await;
+
+        // Both hold at once: each re-INVITE meets the other's and gets a 491
+        let (held_a, held_b) = tokio::join!(a.hold_call(call_id), b.hold_call(call_id));
+        held_a.unwrap();
+        held_b.unwrap();
+
+        assert!(to_a.sent.load(Ordering::SeqCst) >= 2);
+        assert!(to_b.sent.load(Ordering::SeqCst) >= 2);
+        let a_state = dialogs_a.get(call_id).await.unwrap();
+        let b_state = dialogs_b.get(call_id).await.unwrap();
+        assert!(!a_state.has_pending_reinvite() && !b_state.has_pending_reinvite());
+        assert_eq!(a_state.negotiated_direction(), Some(StreamDirection::Inactive));
+        assert_eq!(b_state.negotiated_direction(), Some(StreamDirection::Inactive));
+    }
+
     fn create_test_request(call_id: &str) -> super::super::message::SipRequest {
         let request_str = format!("INVITE sip:bob@example.com SIP/2.0\r\nCall-ID: {}\r\nCSeq: 1 INVITE\r\n\r\n", call_id);
         super::super::message::SipRequest::parse(request_str.as_bytes()).unwrap()
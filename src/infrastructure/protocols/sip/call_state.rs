@@ -1,8 +1,9 @@
 //! Call State Machine
 //!
-//! Implements a complete call state machine for SIP calls
+//! Implements a complete call state machine for SIP calls, timing setup
+//! (INVITE to the first 180/183), ring, talk and hold separately
 
-use std::time::Instant;
+use std::time::{Duration, Instant};
 
 /// Call State
 #[derive(Debug, Clone, PartialEq, Eq)]
@@ -68,6 +69,16 @@ pub enum CallLeg {
     Callee,
 }
 
+impl CallLeg {
+    /// The leg at the other end of the call
+    pub fn other(&self) -> CallLeg {
+        match self {
+            CallLeg::Caller => CallLeg::Callee,
+            CallLeg::Callee => CallLeg::Caller,
+        }
+    }
+}
+
 /// Call Direction
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub enum CallDirection {
@@ -82,27 +93,78 @@ pub enum CallDirection {
 pub struct CallStats {
     /// When the call was created
     pub created_at: Instant,
+    /// First 180 Ringing or 183 Session Progress (if any)
+    pub ringing_at: Option<Instant>,
     /// When the call was answered (if applicable)
     pub answered_at: Option<Instant>,
     /// When the call ended (if applicable)
     pub ended_at: Option<Instant>,
     /// Number of provisional responses received/sent
     pub provisional_count: u32,
+    /// Each state entered after the first, with when
+    pub transitions: Vec<(CallState, Instant)>,
+    /// Start of the running hold segment, while on hold
+    pub held_since: Option<Instant>,
+    /// Time on hold in finished segments
+    pub hold_time: Duration,
+    /// Number of hold segments
+    pub hold_count: u32,
 }
 
 impl CallStats {
     pub fn new() -> Self {
+        Self::starting_at(Instant::now())
+    }
+
+    /// Stats of a call created at `created_at`
+    pub fn starting_at(created_at: Instant) -> Self {
         Self {
-            created_at: Instant::now(),
+            created_at,
+            ringing_at: None,
             answered_at: None,
             ended_at: None,
             provisional_count: 0,
+            transitions: Vec::new(),
+            held_since: None,
+            hold_time: Duration::ZERO,
+            hold_count: 0,
         }
     }
 
-    /// Get call setup duration (time from created to answered)
-    pub fn setup_duration(&self) -> Option<std::time::Duration> {
-        self.answered_at.map(|t| t.duration_since(self.created_at))
+    /// Get call setup duration (time from created to the first 180/183,
+    /// or to the answer when there was none)
+    pub fn setup_duration(&self) -> Option<Duration> {
+        self.ringing_at
+            .or(self.answered_at)
+            .map(|t| t.duration_since(self.created_at))
+    }
+
+    /// Time alerting, from the first 180/183 to the answer, the end, or
+    /// `now` while still alerting
+    pub fn ring_duration(&self, now: Instant) -> Option<Duration> {
+        let ringing_at = self.ringing_at?;
+        let until = self.answered_at.or(self.ended_at).unwrap_or(now);
+        Some(until.saturating_duration_since(ringing_at))
+    }
+
+    /// Time on hold as of `now`, including a running segment
+    pub fn hold_duration(&self, now: Instant) -> Duration {
+        let running = self
+            .held_since
+            .map_or(Duration::ZERO, |since| now.saturating_duration_since(since));
+        self.hold_time + running
+    }
+
+    /// Time answered and not on hold as of `now`; zero for calls never
+    /// answered, and paused while on hold
+    pub fn talk_duration(&self, now: Instant) -> Duration {
+        let Some(answered_at) = self.answered_at else {
+            return Duration::ZERO;
+        };
+        let until = self.ended_at.unwrap_or(now);
+        until
+            .saturating_duration_since(answered_at)
+            .saturating_sub(self.hold_duration(until))
     }
 
     /// Get call duration (time from answered to ended)
@@ -154,9 +216,14 @@ pub struct CallStateMachine {
 
 impl CallStateMachine {
     pub fn new() -> Self {
+        Self::starting_at(Instant::now())
+    }
+
+    /// State machine of a call whose INVITE came at `created_at`
+    pub fn starting_at(created_at: Instant) -> Self {
         Self {
             state: CallState::Trying,
-            stats: CallStats::new(),
+            stats: CallStats::starting_at(created_at),
         }
     }
 
@@ -172,6 +239,11 @@ impl CallStateMachine {
 
     /// Process an event and transition state
     pub fn process_event(&mut self, event: CallEvent) -> Result<(), String> {
+        self.process_event_at(event, Instant::now())
+    }
+
+    /// Process an event that happened at `at`
+    pub fn process_event_at(&mut self, event: CallEvent, at: Instant) -> Result<(), String> {
         let new_state = match (&self.state, &event) {
             // From Trying
             (CallState::Trying, CallEvent::Trying) => CallState::Proceeding,
@@ -216,22 +288,60 @@ impl CallStateMachine {
 
         // Update statistics
         match event {
-            CallEvent::Trying | CallEvent::Ringing | CallEvent::SessionProgress => {
+            CallEvent::Trying => {
                 self.stats.provisional_count += 1;
             }
+            CallEvent::Ringing | CallEvent::SessionProgress => {
+                self.stats.provisional_count += 1;
+                self.stats.ringing_at.get_or_insert(at);
+            }
             CallEvent::Answer => {
-                self.stats.answered_at = Some(Instant::now());
+                self.stats.answered_at = Some(at);
             }
             CallEvent::Bye | CallEvent::Reject | CallEvent::Timeout => {
-                self.stats.ended_at = Some(Instant::now());
+                self.end_hold(at);
+                self.stats.ended_at = Some(at);
             }
             _ => {}
         }
 
+        self.stats.transitions.push((new_state.clone(), at));
         self.state = new_state;
         Ok(())
     }
 
+    /// Whether talk time is paused for a hold
+    pub fn is_held(&self) -> bool {
+        self.stats.held_since.is_some()
+    }
+
+    /// Start a hold segment at `at`, pausing talk time; returns false when
+    /// the call is not established or already held
+    pub fn hold_at(&mut self, at: Instant) -> bool {
+        if !self.state.is_established() || self.is_held() {
+            return false;
+        }
+        self.stats.held_since = Some(at);
+        self.stats.hold_count += 1;
+        true
+    }
+
+    /// End the hold segment at `at`, resuming talk time; returns false when
+    /// the call was not held
+    pub fn resume_at(&mut self, at: Instant) -> bool {
+        self.end_hold(at)
+    }
+
+    fn end_hold(&mut self, at: Instant) -> bool {
+        match self.stats.held_since.take() {
+            Some(since) => {
+                self.stats.hold_time += at.saturating_duration_since(since);
+                true
+            }
+            None => false,
+        }
+    }
+
     /// Check if call can be answered
     pub fn can_answer(&self) -> bool {
         matches!(
@@ -332,6 +442,51 @@ mod tests {
         assert!(sm.stats().setup_duration().is_some());
     }
 
+    #[test]
+    fn test_duration_breakdown_with_two_holds() {
+        let invite = Instant::now();
+        let at = |secs| invite + Duration::from_secs(secs);
+        let mut sm = CallStateMachine::starting_at(invite);
+
+        sm.process_event_at(CallEvent::Trying, at(0)).unwrap();
+        sm.process_event_at(CallEvent::Ringing, at(3)).unwrap();
+        sm.process_event_at(CallEvent::Answer, at(15)).unwrap();
+        assert!(sm.hold_at(at(45)));
+        assert!(!sm.hold_at(at(50)));
+        assert_eq!(sm.stats().talk_duration(at(60)), Duration::from_secs(30));
+        assert!(sm.resume_at(at(65)));
+        assert!(sm.hold_at(at(100)));
+        sm.process_event_at(CallEvent::Bye, at(130)).unwrap();
+
+        let stats = sm.stats();
+        assert_eq!(stats.setup_duration(), Some(Duration::from_secs(3)));
+        assert_eq!(stats.ring_duration(at(200)), Some(Duration::from_secs(12)));
+        assert_eq!(stats.hold_count, 2);
+        assert_eq!(stats.hold_duration(at(200)), Duration::from_secs(50));
+        assert_eq!(stats.talk_duration(at(200)), Duration::from_secs(65));
+        assert_eq!(stats.call_duration(), Some(Duration::from_secs(115)));
+        assert_eq!(
+            stats.transitions.iter().map(|(state, _)| state.name()).collect::<Vec<_>>(),
+            vec!["Proceeding", "Ringing", "Established", "Terminating"]
+        );
+    }
+
+    #[test]
+    fn test_unanswered_call_has_ring_time_only() {
+        let invite = Instant::now();
+        let at = |secs| invite + Duration::from_secs(secs);
+        let mut sm = CallStateMachine::starting_at(invite);
+
+        sm.process_event_at(CallEvent::SessionProgress, at(2)).unwrap();
+        assert!(!sm.hold_at(at(5)));
+        sm.process_event_at(CallEvent::Reject, at(20)).unwrap();
+
+        let stats = sm.stats();
+        assert_eq!(stats.setup_duration(), Some(Duration::from_secs(2)));
+        assert_eq!(stats.ring_duration(at(60)), Some(Duration::from_secs(18)));
+        assert_eq!(stats.talk_duration(at(60)), Duration::ZERO);
+    }
+
     #[test]
     fn test_state_helpers() {
         assert!(CallState::Trying.is_active());
@@ -0,0 +1,418 @@
+//! Campaign calls over SIP
+//!
+//! The INVITE of a campaign call goes through the call router like any
+//! other outbound call (number portability, header rules, trunk, redirects).
+//! An answered call hears the campaign's announcement, or runs its IVR flow,
+//! and may press the confirmation digit. A far end that hangs up before the
+//! content is over is reported with how long after answering it did.
+
+use super::call_router::CallRouter;
+use super::message::{SipError, SipRequest};
+use super::redirect::{InviteForwarder, TrustZone};
+use crate::application::campaign::{CampaignDialer, DialAttempt};
+use crate::domain::call::EndReason;
+use crate::domain::call_announcer::{AnnouncementRequest, AnnouncementType, CallAnnouncer};
+use crate::domain::campaign::{CampaignContent, DialResult};
+use crate::infrastructure::ivr::{
+    collect_digits, DtmfDispatcher, DtmfEvent, IvrFlow, IvrFlowEngine,
+};
+use async_trait::async_trait;
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::Instant;
+use tokio::sync::broadcast;
+use tracing::{debug, warn};
+use uuid::Uuid;
+
+/// Places campaign calls through a [`CallRouter`]
+pub struct SipCampaignDialer {
+    router: Arc<CallRouter>,
+    forwarder: Arc<dyn InviteForwarder>,
+    /// Domain of targets given as bare numbers
+    domain: String,
+    announcer: Option<Arc<CallAnnouncer>>,
+    dtmf: Option<Arc<DtmfDispatcher>>,
+    ivr: Option<Arc<IvrFlowEngine>>,
+    flows: HashMap<String, IvrFlow>,
+}
+
+impl SipCampaignDialer {
+    pub fn new(router: Arc<CallRouter>, forwarder: Arc<dyn InviteForwarder>, domain: &str) -> Self {
+        Self {
+            router,
+            forwarder,
+            domain: domain.to_string(),
+            announcer: None,
+            dtmf: None,
+            ivr: None,
+            flows: HashMap::new(),
+        }
+    }
+
+    /// Play announcements with `announcer`
+    pub fn with_announcer(mut self, announcer: Arc<CallAnnouncer>) -> Self {
+        self.announcer = Some(announcer);
+        self
+    }
+
+    /// Take confirmation digits and IVR input from `dtmf`
+    pub fn with_dtmf(mut self, dtmf: Arc<DtmfDispatcher>) -> Self {
+        self.dtmf = Some(dtmf);
+        self
+    }
+
+    /// Run IVR flows with `engine`
+    pub fn with_ivr(mut self, engine: Arc<IvrFlowEngine>) -> Self {
+        self.ivr = Some(engine);
+        self
+    }
+
+    /// Make `flow` available to campaigns by its id
+    pub fn with_ivr_flow(mut self, flow: IvrFlow) -> Self {
+        self.flows.insert(flow.id.clone(), flow);
+        self
+    }
+
+    fn uri(&self, number: &str) -> String {
+        if number.starts_with("sip:") || number.starts_with("sips:") {
+            number.to_string()
+        } else {
+            format!("sip:{}@{}", number, self.domain)
+        }
+    }
+
+    fn play(&self, call_id: &str, prompts: &[String]) {
+        let Some(announcer) = &self.announcer else {
+            warn!(
+                "No call announcer configured, cannot play {:?} to call {}",
+                prompts, call_id
+            );
+            return;
+        };
+        let announcement = prompts
+            .iter()
+            .fold(
+                AnnouncementRequest::new(call_id.to_string(), AnnouncementType::Custom),
+                |announcement, prompt| announcement.add_audio(prompt),
+            )
+            .immediate();
+        if let Err(e) = announcer.play_announcement(announcement) {
+            warn!("Failed to play {:?} to call {}: {}", prompts, call_id, e);
+        }
+    }
+
+    /// Run the content of an answered call; returns the digits pressed
+    async fn run_content(
+        &self,
+        attempt: &DialAttempt,
+        digits: Option<&mut broadcast::Receiver<DtmfEvent>>,
+    ) -> String {
+        match &attempt.content {
+            CampaignContent::Announcement { prompts } => {
+                self.play(&attempt.call_id, prompts);
+                match (attempt.confirm_digit, digits) {
+                    (Some(_), Some(digits)) => collect_digits(digits, 1, attempt.listen)
+                        .await
+                        .unwrap_or_default(),
+                    _ => {
+                        tokio::time::sleep(attempt.listen).await;
+                        String::new()
+                    }
+                }
+            }
+            CampaignContent::IvrFlow { flow_id } => {
+                match (&self.ivr, self.flows.get(flow_id), digits) {
+                    (Some(ivr), Some(flow), Some(digits)) => {
+                        let greeting = flow
+                            .get_menu_system()
+                            .await
+                            .get_menu(&flow.start_menu_id)
+                            .map(|menu| menu.greeting_file.clone());
+                        self.play(&attempt.call_id, &greeting.into_iter().collect::<Vec<_>>());
+                        let outcome = ivr
+                            .run(&attempt.call_id, flow, digits, |prompts| {
+                                self.play(&attempt.call_id, prompts)
+                            })
+                            .await;
+                        debug!("Campaign call {} IVR finished: {:?}", attempt.call_id, outcome);
+                    }
+                    _ => {
+                        warn!(
+                            "IVR flow {} not available, hanging up campaign call {}",
+                            flow_id, attempt.call_id
+                        );
+                    }
+                }
+                String::new()
+            }
+        }
+    }
+}
+
+/// INVITE placing a campaign call from `caller_uri` to `target`
+fn campaign_invite(call_id: &str, caller_uri: &str, target: &str) -> Result<SipRequest, SipError> {
+    let request = format!(
+        "INVITE {target} SIP/2.0\r\n\
+         Max-Forwards: 70\r\n\
+         From: <{caller}>;tag={tag}\r\n\
+         To: <{target}>\r\n\
+         Call-ID: {call_id}\r\n\
+         CSeq: 1 INVITE\r\n\
+         Content-Length: 0\r\n\r\n",
+        target = target,
+        caller = caller_uri,
+        tag = Uuid::new_v4().simple(),
+        call_id = call_id,
+    );
+    SipRequest::parse(request.as_bytes())
+}
+
+#[async_trait]
+impl CampaignDialer for SipCampaignDialer {
+    async fn dial(&self, attempt: &DialAttempt) -> DialResult {
+        let call_id = attempt.call_id.as_str();
+        let caller_uri = self.uri(&attempt.caller_id);
+        let target = self.uri(&attempt.number);
+        if let Err(e) = self
+            .router
+            .create_call(call_id.to_string(), caller_uri.clone(), target.clone())
+            .await
+        {
+            return DialResult::failed(&e);
+        }
+        if let Some(trunk) = &attempt.trunk {
+            self.router.set_trunk(call_id, trunk).await;
+        }
+
+        let response = match campaign_invite(call_id, &caller_uri, &target) {
+            Ok(invite) => {
+                self.router
+                    .forward_with_redirects(
+                        call_id,
+                        &invite,
+                        &target,
+                        TrustZone::Internal,
+                        self.forwarder.as_ref(),
+                    )
+                    .await
+            }
+            Err(e) => Err(e),
+        };
+        let status = match response {
+            Ok(response) => response.status_code(),
+            Err(e) => {
+                warn!("Campaign call {} to {} failed: {}", call_id, target, e);
+                self.router.discard_call(call_id).await;
+                return DialResult::failed(&e.to_string());
+            }
+        };
+        if !(200..300).contains(&status) {
+            let reason = match status {
+                486 | 600 => EndReason::Busy,
+                408 | 480 | 487 => EndReason::NoAnswer,
+                _ => EndReason::Rejected,
+            };
+            let _ = self.router.end_call(call_id, reason).await;
+            return DialResult::rejected(status);
+        }
+
+        // Subscribed before answering so that no digit is missed
+        let mut digits = self.dtmf.as_ref().map(|dtmf| dtmf.subscribe(call_id));
+        if let Err(e) = self.router.answer_call(call_id).await {
+            warn!("Failed to answer campaign call {}: {}", call_id, e);
+        }
+        let answered_at = Instant::now();
+        // The state sender is dropped once the far end's BYE removes the call
+        let mut state = self.router.watch_call_state(call_id).await;
+        let hung_up = async {
+            if let Some(state) = state.as_mut() {
+                while state.changed().await.is_ok() {}
+            }
+        };
+
+        let digits = tokio::select! {
+            digits = self.run_content(attempt, digits.as_mut()) => Some(digits),
+            _ = hung_up => None,
+        };
+        match digits {
+            Some(digits) => {
+                // Content over: the PBX hangs up
+                let _ = self.router.end_call(call_id, EndReason::NormalClearing).await;
+                DialResult::answered().with_digits(&digits)
+            }
+            None => DialResult::answered().with_hangup_after(answered_at.elapsed()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::application::campaign::CampaignService;
+    use crate::domain::campaign::{
+        CampaignReport, CampaignSettings, CampaignStatus, CampaignTarget, TargetOutcome,
+    };
+    use crate::infrastructure::ivr::DtmfDigit;
+    use crate::infrastructure::protocols::sip::call_state::CallState;
+    use crate::infrastructure::protocols::sip::message::SipResponse;
+    use crate::infrastructure::protocols::sip::registrar::Registrar;
+    use crate::test_support::{MockUa, Scenario};
+    use std::time::Duration;
+    use tokio::sync::mpsc;
+
+    /// Mock UAS endpoints by AOR
+    struct Endpoints(HashMap<String, MockUa>);
+
+    #[async_trait]
+    impl InviteForwarder for Endpoints {
+        async fn forward(&self, target: &str, request: &SipRequest) -> Result<SipResponse, SipError> {
+            self.forward_with_progress(target, request, mpsc::unbounded_channel().0)
+                .await
+        }
+
+        async fn forward_with_progress(
+            &self,
+            target: &str,
+            request: &SipRequest,
+            progress: mpsc::UnboundedSender<u16>,
+        ) -> Result<SipResponse, SipError> {
+            match self.0.get(target) {
+                Some(ua) => ua.forward_with_progress(target, request, progress).await,
+                None => Err(SipError::TransportError(format!("{} unreachable", target))),
+            }
+        }
+    }
+
+    /// Wait until campaign call `call_id` is answered
+    async fn answered(router: &CallRouter, call_id: &str) {
+        for _ in 0..500 {
+            if router.get_call_state(call_id).await == Some(CallState::Established) {
+                return;
+            }
+            tokio::time::sleep(Duration::from_millis(5)).await;
+        }
+        panic!("call {} was not answered", call_id);
+    }
+
+    async fn run_to_end(service: &Arc<CampaignService>, id: Uuid) -> CampaignReport {
+        for _ in 0..1000 {
+            service.dial_due();
+            let report = service.get(id).unwrap();
+            if report.status == CampaignStatus::Completed {
+                return report;
+            }
+            tokio::time::sleep(Duration::from_millis(5)).await;
+        }
+        panic!("campaign {} did not complete", id);
+    }
+
+    #[tokio::test]
+    async fn test_campaign_against_mock_endpoints() {
+        let mut endpoints = HashMap::new();
+        let scenarios = [
+            ("confirms", Scenario::answer()),
+            ("listens", Scenario::ring_then_answer(Duration::from_millis(20))),
+            ("machine", Scenario::answer()),
+            ("busy", Scenario::reject(486)),
+            ("absent", Scenario::ring_then_reject(Duration::from_millis(20), 480)),
+            ("gone", Scenario::reject(404)),
+        ];
+        for (user, scenario) in scenarios {
+            let ua = MockUa::bind(&format!("sip:{}@example.com", user)).await.unwrap();
+            ua.set_scenario(scenario);
+            endpoints.insert(ua.aor().to_string(), ua);
+        }
+        let router = Arc::new(CallRouter::new(Arc::new(Registrar::new())));
+        let dtmf = Arc::new(DtmfDispatcher::new());
+        let endpoints = Arc::new(Endpoints(endpoints));
+        let dialer = SipCampaignDialer::new(router.clone(), endpoints.clone(), "example.com")
+            .with_dtmf(dtmf.clone());
+        let service = Arc::new(CampaignService::new(Arc::new(dialer)));
+
+        let mut settings = CampaignSettings::new(
+            "Clinic reminder",
+            "clinic",
+            ["confirms", "listens", "machine", "busy", "absent", "gone"]
+                .iter()
+                .map(|user| CampaignTarget::new(user))
+                .collect(),
+            CampaignContent::Announcement {
+                prompts: vec!["appointment-reminder".to_string()],
+            },
+        );
+        settings.confirm_digit = Some('1');
+        settings.confirm_timeout_secs = 1;
+        settings.machine_hangup_secs = 1;
+        settings.calls_per_second = 10.0;
+        settings.max_concurrent = 3;
+        settings.retry.max_attempts = 2;
+        settings.retry.retry_delay_secs = 1;
+        let campaign = service.create(settings).unwrap();
+        let call_id = |index: usize, attempt: u32| {
+            format!("campaign-{}-{}-{}", campaign.id, index, attempt)
+        };
+
+        // The first target presses 1, the machine hangs up at once
+        let confirm = {
+            let (router, dtmf, call_id) = (router.clone(), dtmf.clone(), call_id(0, 1));
+            tokio::spawn(async move {
+                answered(&router, &call_id).await;
+                dtmf.publish(&call_id, DtmfEvent::new(DtmfDigit::One, Duration::from_millis(100)));
+            })
+        };
+        let machine = {
+            let (router, call_id) = (router.clone(), call_id(2, 1));
+            tokio::spawn(async move {
+                answered(&router, &call_id).await;
+                router.terminate_call(&call_id).await.unwrap();
+            })
+        };
+        // The busy target answers the retry
+        let free = {
+            let (service, endpoints, id) = (service.clone(), endpoints.clone(), campaign.id);
+            tokio::spawn(async move {
+                while service.get(id).unwrap().targets[3].last_result.as_deref() != Some("Busy") {
+                    tokio::time::sleep(Duration::from_millis(5)).await;
+                }
+                endpoints.0["sip:busy@example.com"].set_scenario(Scenario::answer());
+            })
+        };
+
+        let report = run_to_end(&service, campaign.id).await;
+        confirm.await.unwrap();
+        machine.await.unwrap();
+        free.await.unwrap();
+
+        let outcome = |number: &str| {
+            report
+                .targets
+                .iter()
+                .find(|t| t.number == number)
+                .and_then(|t| t.outcome.clone())
+        };
+        assert_eq!(outcome("confirms"), Some(TargetOutcome::Confirmed));
+        assert_eq!(outcome("listens"), Some(TargetOutcome::Answered));
+        assert_eq!(outcome("machine"), Some(TargetOutcome::MachineSuspected));
+        assert_eq!(outcome("busy"), Some(TargetOutcome::Answered));
+        assert_eq!(
+            outcome("absent"),
+            Some(TargetOutcome::Failed {
+                cause: "No answer".to_string()
+            })
+        );
+        assert_eq!(
+            outcome("gone"),
+            Some(TargetOutcome::Failed {
+                cause: "Rejected with 404".to_string()
+            })
+        );
+        assert_eq!(
+            (report.confirmed, report.answered, report.machine_suspected, report.failed),
+            (1, 2, 1, 2)
+        );
+        // Busy and absent were called twice
+        assert_eq!(report.attempts, 8);
+        assert_eq!(endpoints.0["sip:absent@example.com"].received().len(), 2);
+        assert_eq!(router.active_call_count().await, 0);
+    }
+}
@@ -0,0 +1,645 @@
+//! Fallback from Opus to G.711 when decoding fails mid-call
+//!
+//! A leg whose Opus packets keep arriving undecodable (see
+//! [`DecodeHealth`](crate::infrastructure::media::DecodeHealth)) is heard
+//! as concealment noise. [`CodecFallback`] watches the decode error rate of
+//! Opus legs: once it stays above the policy's maximum for the sustain
+//! period, a re-INVITE offers the leg only PCMU and PCMA. Media is relayed
+//! between the legs without transcoding, so when the other leg is on Opus
+//! too it is moved to the same codec; if it refuses, the first leg is moved
+//! back. Each fallback is noted on the call's CDR.
+//!
+//! When the legs cannot be moved, the call stays up on Opus and the
+//! problem leg's undecodable packets are passed on as comfort noise.
+
+use super::call_router::{CallRouter, LegMedia};
+use super::call_state::CallLeg;
+use super::dialog::{LocalReinviteOutcome, ReinviteSender};
+use super::sdp::{SdpMedia, SdpSession};
+use super::srtp_rekey::leg_name;
+use crate::infrastructure::media::{CodecInfo, MediaStream, PayloadMap};
+use metrics::counter;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::sync::broadcast;
+use tokio::task::JoinHandle;
+use tracing::{debug, info, warn};
+
+/// When calls fall back from Opus
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct CodecFallbackPolicy {
+    pub enabled: bool,
+    /// Share of a leg's Opus packets lost to decode errors, after FEC, above
+    /// which the leg is failing
+    pub max_error_rate: f64,
+    /// Seconds a leg must keep failing before it falls back
+    pub sustain_secs: u64,
+}
+
+impl Default for CodecFallbackPolicy {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            max_error_rate: 0.2,
+            sustain_secs: 5,
+        }
+    }
+}
+
+impl CodecFallbackPolicy {
+    pub fn validate(&self) -> Result<(), String> {
+        if !(self.max_error_rate > 0.0 && self.max_error_rate < 1.0) {
+            return Err("max_error_rate must be between 0 and 1".to_string());
+        }
+        if self.sustain_secs == 0 || self.sustain_secs > 60 {
+            return Err("sustain_secs must be between 1 and 60".to_string());
+        }
+        Ok(())
+    }
+
+    pub fn sustain(&self) -> Duration {
+        Duration::from_secs(self.sustain_secs)
+    }
+}
+
+/// Published when a failing leg is dealt with
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum CodecFallbackEvent {
+    /// The leg (and the other one, if it was on Opus) moved to `codec`
+    FellBack {
+        call_id: String,
+        leg: String,
+        codec: String,
+        error_rate: f64,
+    },
+    /// The call stays on Opus, with comfort noise for the undecodable
+    /// packets; `status` is the refusal, if a re-INVITE was answered
+    Failed {
+        call_id: String,
+        leg: String,
+        status: Option<u16>,
+    },
+}
+
+fn on_opus(stream: &MediaStream) -> bool {
+    stream
+        .payloads()
+        .primary()
+        .is_some_and(|codec| codec.name.eq_ignore_ascii_case("opus"))
+}
+
+fn g711_codecs() -> Vec<CodecInfo> {
+    vec![
+        CodecInfo::new(0, "PCMU".to_string(), 8000),
+        CodecInfo::new(8, "PCMA".to_string(), 8000),
+    ]
+}
+
+fn telephone_event(media: &SdpMedia) -> Option<u8> {
+    media
+        .rtpmap
+        .iter()
+        .find(|(_, encoding)| encoding.to_ascii_lowercase().starts_with("telephone-event/"))
+        .and_then(|(pt, _)| pt.parse().ok())
+}
+
+/// `local` offering only `codecs`, and telephone-events as before
+fn restricted_offer(mut local: SdpSession, codecs: &[CodecInfo]) -> SdpSession {
+    if let Some(audio) = local.media.iter_mut().find(|m| m.media_type == "audio") {
+        let telephone_event = telephone_event(audio);
+        audio.set_payloads(&PayloadMap {
+            codecs: codecs.to_vec(),
+            telephone_event,
+            opus: None,
+        });
+    }
+    local
+}
+
+/// The first of `codecs` the answer accepted
+fn answered_payloads(answer: &SdpSession, codecs: &[CodecInfo]) -> Option<PayloadMap> {
+    let audio = answer.audio_media()?;
+    let codec = audio
+        .formats
+        .iter()
+        .filter_map(|format| format.parse::<u8>().ok())
+        .find_map(|pt| codecs.iter().find(|codec| codec.payload_type == pt))?;
+    Some(PayloadMap {
+        codecs: vec![codec.clone()],
+        telephone_event: telephone_event(audio),
+        opus: None,
+    })
+}
+
+/// Why a leg was not moved
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum NotMoved {
+    /// Refused, with the final status if the re-INVITE was answered
+    Refused(Option<u16>),
+    /// Still in glare, or another re-INVITE outstanding, after every attempt
+    Busy,
+}
+
+/// Moves calls whose Opus media cannot be decoded to G.711
+pub struct CodecFallback {
+    router: Arc<CallRouter>,
+    policy: CodecFallbackPolicy,
+    sender: Option<Arc<dyn ReinviteSender>>,
+    /// Since when each failing leg has been failing, keyed by call and leg
+    failing_since: Mutex<HashMap<(String, &'static str), Instant>>,
+    /// Legs moved or given up on
+    handled: Mutex<HashSet<(String, &'static str)>>,
+    /// Legs being moved
+    in_flight: Mutex<HashSet<(String, &'static str)>>,
+    events: broadcast::Sender<CodecFallbackEvent>,
+}
+
+impl CodecFallback {
+    pub fn new(router: Arc<CallRouter>, policy: CodecFallbackPolicy) -> Self {
+        let (events, _) = broadcast::channel(256);
+        Self {
+            router,
+            policy,
+            sender: None,
+            failing_since: Mutex::new(HashMap::new()),
+            handled: Mutex::new(HashSet::new()),
+            in_flight: Mutex::new(HashSet::new()),
+            events,
+        }
+    }
+
+    /// Send the re-INVITEs; without one, failing legs only get comfort
+    /// noise
+    pub fn with_sender(mut self, sender: Arc<dyn ReinviteSender>) -> Self {
+        self.sender = Some(sender);
+        self
+    }
+
+    /// Fallback events
+    pub fn subscribe(&self) -> broadcast::Receiver<CodecFallbackEvent> {
+        self.events.subscribe()
+    }
+
+    /// Check Opus legs every `tick`
+    pub fn spawn(self: Arc<Self>, tick: Duration) -> JoinHandle<()> {
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(tick);
+            loop {
+                interval.tick().await;
+                self.check_at(Instant::now()).await;
+            }
+        })
+    }
+
+    /// Start the fallbacks due as of `now`
+    pub async fn check_at(self: &Arc<Self>, now: Instant) {
+        let media = self.router.established_media().await;
+        let present = |key: &(String, &'static str)| {
+            media
+                .iter()
+                .any(|m| m.call_id == key.0 && leg_name(&m.leg) == key.1)
+        };
+        self.failing_since.lock().unwrap().retain(|key, _| present(key));
+        self.handled.lock().unwrap().retain(|key| present(key));
+
+        for leg in &media {
+            let key = (leg.call_id.clone(), leg_name(&leg.leg));
+            if !on_opus(&leg.stream)
+                || self.handled.lock().unwrap().contains(&key)
+                || self.in_flight.lock().unwrap().contains(&key)
+            {
+                continue;
+            }
+            let error_rate = leg.stream.decode_error_rate();
+            {
+                let mut failing_since = self.failing_since.lock().unwrap();
+                if error_rate <= self.policy.max_error_rate {
+                    failing_since.remove(&key);
+                    continue;
+                }
+                let since = *failing_since.entry(key.clone()).or_insert(now);
+                if now.saturating_duration_since(since) < self.policy.sustain() {
+                    continue;
+                }
+                failing_since.remove(&key);
+            }
+            self.in_flight.lock().unwrap().insert(key.clone());
+
+            let other = media
+                .iter()
+                .find(|m| m.call_id == leg.call_id && m.leg != leg.leg)
+                .cloned();
+            let fallback = self.clone();
+            let leg = leg.clone();
+            tokio::spawn(async move {
+                if fallback.fall_back(&leg, other.as_ref(), error_rate).await {
+                    fallback.handled.lock().unwrap().insert(key.clone());
+                }
+                fallback.in_flight.lock().unwrap().remove(&key);
+            });
+        }
+    }
+
+    /// Move the leg, or conceal its errors; false when it stayed in glare
+    /// and should be tried again
+    async fn fall_back(&self, media: &LegMedia, other: Option<&LegMedia>, error_rate: f64) -> bool {
+        let leg = leg_name(&media.leg);
+        warn!(
+            "Call {} ({}) lost {:.0}% of its Opus packets to decode errors, falling back to G.711",
+            media.call_id,
+            leg,
+            error_rate * 100.0
+        );
+        let Some(sender) = &self.sender else {
+            warn!("No re-INVITE sender, call {} ({}) stays on Opus", media.call_id, leg);
+            media.stream.set_comfort_noise(true);
+            return true;
+        };
+
+        match self.move_to_g711(sender.as_ref(), media, other).await {
+            Ok(codec) => {
+                counter!("media_codec_fallbacks_total", "result" => "fell_back").increment(1);
+                info!("Call {} ({}) fell back to {}", media.call_id, leg, codec.name);
+                self.router
+                    .record_codec_fallback(
+                        &media.call_id,
+                        format!(
+                            "{} moved from Opus to {} at {:.0}% decode errors",
+                            leg,
+                            codec.name,
+                            error_rate * 100.0
+                        ),
+                    )
+                    .await;
+                let _ = self.events.send(CodecFallbackEvent::FellBack {
+                    call_id: media.call_id.clone(),
+                    leg: leg.to_string(),
+                    codec: codec.name,
+                    error_rate,
+                });
+                true
+            }
+            Err(NotMoved::Busy) => {
+                debug!("Call {} ({}) still in glare, falling back later", media.call_id, leg);
+                false
+            }
+            Err(NotMoved::Refused(status)) => {
+                counter!("media_codec_fallbacks_total", "result" => "failed").increment(1);
+                warn!(
+                    "Call {} ({}) could not fall back ({:?}), concealing with comfort noise",
+                    media.call_id, leg, status
+                );
+                media.stream.set_comfort_noise(true);
+                let _ = self.events.send(CodecFallbackEvent::Failed {
+                    call_id: media.call_id.clone(),
+                    leg: leg.to_string(),
+                    status,
+                });
+                true
+            }
+        }
+    }
+
+    /// Move the leg, and the other one if on Opus, to one G.711 codec
+    async fn move_to_g711(
+        &self,
+        sender: &dyn ReinviteSender,
+        media: &LegMedia,
+        other: Option<&LegMedia>,
+    ) -> Result<CodecInfo, NotMoved> {
+        let original = self.local_sdp(media).await.ok_or(NotMoved::Refused(None))?;
+        let previous = media.stream.payloads();
+        let answer = self
+            .reoffer(sender, media, restricted_offer(original.clone(), &g711_codecs()))
+            .await?;
+        let payloads =
+            answered_payloads(&answer, &g711_codecs()).ok_or(NotMoved::Refused(None))?;
+        let codec = payloads.codecs[0].clone();
+        media.stream.switch_payloads(payloads).await;
+
+        let Some(other) = other.filter(|other| on_opus(&other.stream)) else {
+            return Ok(codec);
+        };
+        let moved = match self.local_sdp(other).await {
+            Some(local) => {
+                let codecs = [codec.clone()];
+                match self.reoffer(sender, other, restricted_offer(local, &codecs)).await {
+                    Ok(answer) => answered_payloads(&answer, &codecs).ok_or(NotMoved::Refused(None)),
+                    Err(e) => Err(e),
+                }
+            }
+            None => Err(NotMoved::Refused(None)),
+        };
+        match moved {
+            Ok(payloads) => {
+                other.stream.switch_payloads(payloads).await;
+                Ok(codec)
+            }
+            Err(e) => {
+                warn!(
+                    "Call {} ({}) refused {}, moving {} back to Opus",
+                    other.call_id,
+                    leg_name(&other.leg),
+                    codec.name,
+                    leg_name(&media.leg)
+                );
+                if self.reoffer(sender, media, original).await.is_ok() {
+                    media.stream.switch_payloads(previous).await;
+                }
+                Err(e)
+            }
+        }
+    }
+
+    async fn local_sdp(&self, media: &LegMedia) -> Option<SdpSession> {
+        self.router
+            .dialog_manager()
+            .with_dialog(&media.call_id, media.leg == CallLeg::Callee, |d| {
+                d.last_local_sdp().and_then(SdpSession::parse)
+            })
+            .await
+    }
+
+    /// Re-INVITE the leg with `offer`, returning the answer or why it was
+    /// not accepted
+    ///
+    /// In glare, or while another re-INVITE is outstanding, the offer is
+    /// sent again after the RFC 3261 back-off.
+    async fn reoffer(
+        &self,
+        sender: &dyn ReinviteSender,
+        media: &LegMedia,
+        offer: SdpSession,
+    ) -> Result<SdpSession, NotMoved> {
+        let reoffered = self
+            .router
+            .dialog_manager()
+            .reoffer(&media.call_id, &media.leg, sender, offer)
+            .await;
+        match reoffered {
+            Some((LocalReinviteOutcome::Completed, answer)) => answer
+                .as_deref()
+                .and_then(SdpSession::parse)
+                .ok_or(NotMoved::Refused(None)),
+            Some((LocalReinviteOutcome::RetryAfter(_), _)) => {
+                debug!("Fallback re-INVITE of call {} still in glare", media.call_id);
+                Err(NotMoved::Busy)
+            }
+            Some((LocalReinviteOutcome::Failed(status), _)) => Err(NotMoved::Refused(Some(status))),
+            Some((LocalReinviteOutcome::Pending, _)) | None => Err(NotMoved::Refused(None)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::infrastructure::media::codec::opus::OpusConfig;
+    use crate::infrastructure::media::{RtpPacket, StreamDirection};
+    use crate::infrastructure::protocols::sip::message::SipError;
+    use crate::infrastructure::protocols::sip::registrar::Registrar;
+    use async_trait::async_trait;
+    use bytes::Bytes;
+    use std::net::IpAddr;
+    use tokio::net::UdpSocket;
+
+    /// Answers every re-INVITE with `status`, recording the offers
+    struct Answering {
+        status: u16,
+        offers: Mutex<Vec<String>>,
+    }
+
+    #[async_trait]
+    impl ReinviteSender for Answering {
+        async fn send_reinvite(
+            &self,
+            _call_id: &str,
+            _leg: &CallLeg,
+            _cseq: u32,
+            sdp: &str,
+        ) -> Result<(u16, Option<String>), SipError> {
+            self.offers.lock().unwrap().push(sdp.to_string());
+            let answer = (self.status < 300).then(|| sdp.to_string());
+            Ok((self.status, answer))
+        }
+    }
+
+    /// Answers the first re-INVITE with 491, the next ones with 200
+    struct GlareFirst {
+        offers: Mutex<Vec<String>>,
+    }
+
+    #[async_trait]
+    impl ReinviteSender for GlareFirst {
+        async fn send_reinvite(
+            &self,
+            _call_id: &str,
+            _leg: &CallLeg,
+            _cseq: u32,
+            sdp: &str,
+        ) -> Result<(u16, Option<String>), SipError> {
+            let mut offers = self.offers.lock().unwrap();
+            offers.push(sdp.to_string());
+            if offers.len() == 1 {
+                Ok((491, None))
+            } else {
+                Ok((200, Some(sdp.to_string())))
+            }
+        }
+    }
+
+    fn policy() -> CodecFallbackPolicy {
+        CodecFallbackPolicy {
+            enabled: true,
+            ..Default::default()
+        }
+    }
+
+    /// Established call whose callee leg sends Opus
+    async fn opus_call(router: &CallRouter, port: u16) -> Arc<MediaStream> {
+        router
+            .create_call(
+                "call-opus".to_string(),
+                "sip:alice@example.com".to_string(),
+                "sip:bob@example.com".to_string(),
+            )
+            .await
+            .unwrap();
+        router.answer_call("call-opus").await.unwrap();
+
+        let payloads = PayloadMap {
+            codecs: vec![CodecInfo::new(111, "opus".to_string(), 48000)],
+            telephone_event: Some(101),
+            opus: Some(OpusConfig {
+                fec_enabled: false,
+                ..OpusConfig::voip()
+            }),
+        };
+        let stream = Arc::new(MediaStream::new(port, 111, 48000).await.unwrap());
+        stream.set_payloads(payloads.clone()).await;
+        stream.set_direction(StreamDirection::SendRecv).await;
+        stream.start().await.unwrap();
+        router
+            .set_leg_stream("call-opus", &CallLeg::Callee, stream.clone())
+            .await;
+
+        let ip: IpAddr = "127.0.0.1".parse().unwrap();
+        let mut local = SdpSession::create_audio_session(ip, port);
+        local.media[0].set_payloads(&payloads);
+        let offer = local.to_string();
+        router
+            .dialog_manager()
+            .with_dialog("call-opus", true, |d| d.answer_offer(1, &offer, local))
+            .await;
+        stream
+    }
+
+    /// Send Opus packets whose framing is broken until the stream notices
+    async fn send_corrupted(stream: &MediaStream, port: u16) -> UdpSocket {
+        let phone = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        for sequence in 0..30u16 {
+            let packet = RtpPacket::new(
+                111,
+                sequence,
+                sequence as u32 * 960,
+                0xbad,
+                Bytes::from_static(&[0x0B, 0x00]),
+            );
+            phone.send_to(&packet.serialize(), ("127.0.0.1", port)).await.unwrap();
+        }
+        tokio::time::timeout(Duration::from_secs(2), async {
+            while stream.decode_error_rate() < 0.5 {
+                tokio::time::sleep(Duration::from_millis(5)).await;
+            }
+        })
+        .await
+        .unwrap();
+        phone
+    }
+
+    async fn next_event(events: &mut broadcast::Receiver<CodecFallbackEvent>) -> CodecFallbackEvent {
+        tokio::time::timeout(Duration::from_secs(2), events.recv())
+            .await
+            .unwrap()
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_corrupted_opus_falls_back_to_g711() {
+        let router = Arc::new(CallRouter::new(Arc::new(Registrar::new())));
+        let stream = opus_call(&router, 10190).await;
+        let sender = Arc::new(Answering {
+            status: 200,
+            offers: Mutex::new(Vec::new()),
+        });
+        let fallback = Arc::new(CodecFallback::new(router.clone(), policy()).with_sender(sender.clone()));
+        let mut events = fallback.subscribe();
+
+        let _phone = send_corrupted(&stream, 10190).await;
+        assert!(stream.decode_counters().concealed >= 10);
+
+        // Failing, but not yet for long enough
+        let start = Instant::now();
+        fallback.check_at(start).await;
+        fallback.check_at(start + Duration::from_secs(2)).await;
+        assert!(sender.offers.lock().unwrap().is_empty());
+
+        fallback.check_at(start + Duration::from_secs(5)).await;
+        match next_event(&mut events).await {
+            CodecFallbackEvent::FellBack { call_id, leg, codec, error_rate } => {
+                assert_eq!(call_id, "call-opus");
+                assert_eq!(leg, "callee");
+                assert_eq!(codec, "PCMU");
+                assert!(error_rate > 0.5);
+            }
+            other => panic!("unexpected {:?}", other),
+        }
+        let offer = sender.offers.lock().unwrap()[0].clone();
+        assert!(offer.contains("m=audio 10190 RTP/AVP 0 8 101"));
+        assert!(!offer.contains("opus"));
+
+        // The stream sends PCMU now, and the call is still up
+        let payloads = stream.payloads();
+        assert_eq!(payloads.primary().unwrap().name, "PCMU");
+        assert_eq!(payloads.telephone_event, Some(101));
+        assert_eq!(stream.decode_error_rate(), 0.0);
+        assert!(!stream.is_closed());
+        assert_eq!(router.established_media().await.len(), 1);
+
+        // A leg moved off Opus is not checked again
+        fallback.check_at(start + Duration::from_secs(20)).await;
+        assert_eq!(sender.offers.lock().unwrap().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_refused_fallback_conceals_with_comfort_noise() {
+        let router = Arc::new(CallRouter::new(Arc::new(Registrar::new())));
+        let stream = opus_call(&router, 10200).await;
+        let sender = Arc::new(Answering {
+            status: 488,
+            offers: Mutex::new(Vec::new()),
+        });
+        let fallback = Arc::new(CodecFallback::new(router.clone(), policy()).with_sender(sender));
+        let mut events = fallback.subscribe();
+
+        let phone = send_corrupted(&stream, 10200).await;
+        let start = Instant::now();
+        fallback.check_at(start).await;
+        fallback.check_at(start + Duration::from_secs(5)).await;
+        assert_eq!(
+            next_event(&mut events).await,
+            CodecFallbackEvent::Failed {
+                call_id: "call-opus".to_string(),
+                leg: "callee".to_string(),
+                status: Some(488),
+            }
+        );
+        assert_eq!(stream.payloads().primary().unwrap().name, "opus");
+        assert!(stream.comfort_noise());
+
+        // Undecodable packets reach the bridge as comfort noise
+        let mut received = stream.subscribe();
+        let packet = RtpPacket::new(111, 100, 96_000, 0xbad, Bytes::from_static(&[0x0B, 0x00]));
+        phone.send_to(&packet.serialize(), ("127.0.0.1", 10200)).await.unwrap();
+        let relayed = tokio::time::timeout(Duration::from_secs(2), received.recv())
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(relayed.payload_type, 13);
+        assert_eq!(relayed.sequence, 100);
+        assert_eq!(router.established_media().await.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_fallback_retried_after_glare() {
+        let router = Arc::new(CallRouter::new(Arc::new(Registrar::new())));
+        let stream = opus_call(&router, 10210).await;
+        let sender = Arc::new(GlareFirst {
+            offers: Mutex::new(Vec::new()),
+        });
+        let fallback = Arc::new(CodecFallback::new(router.clone(), policy()).with_sender(sender.clone()));
+        let mut events = fallback.subscribe();
+
+        let _phone = send_corrupted(&stream, 10210).await;
+        // Skip the glare back-off
+        tokio::time::pause();
+        let start = Instant::now();
+        fallback.check_at(start).await;
+        fallback.check_at(start + Duration::from_secs(5)).await;
+
+        // Still moving: a check meanwhile sends nothing more
+        fallback.check_at(start + Duration::from_secs(6)).await;
+        let event = tokio::time::timeout(Duration::from_secs(10), events.recv())
+            .await
+            .unwrap()
+            .unwrap();
+        assert!(matches!(event, CodecFallbackEvent::FellBack { .. }), "{:?}", event);
+        assert_eq!(sender.offers.lock().unwrap().len(), 2);
+        assert_eq!(stream.payloads().primary().unwrap().name, "PCMU");
+        assert!(!stream.comfort_noise());
+    }
+}
@@ -0,0 +1,511 @@
+//! Connection management for stream-oriented SIP transports (TCP/TLS)
+//!
+//! SIP over TCP requires that responses travel back on the connection the
+//! request arrived on, and NAT'd clients can usually only be reached through
+//! the connection they opened themselves. The connection table keeps track of
+//! open connections keyed by remote address (plus aliases learned from Via and
+//! Contact headers), so both responses and new outbound requests reuse them.
+//!
+//! Also handles:
+//! - CRLF keep-alive (RFC 5626 section 3.5.1): a double-CRLF ping is answered
+//!   with a single CRLF pong
+//! - Idle timeout for connections without traffic
+//! - A maximum connection count with least-recently-used eviction that never
+//!   evicts a connection carrying an active dialog
+//!
+//! Evicted, reaped and closed connections have their reader and writer tasks
+//! aborted, which closes the socket.
+
+use bytes::Bytes;
+use metrics::{counter, gauge};
+use std::collections::{HashMap, HashSet};
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::sync::{mpsc, RwLock};
+use tokio::task::AbortHandle;
+use tracing::{debug, info, warn};
+
+/// CRLF keep-alive ping (RFC 5626 section 3.5.1)
+pub const KEEPALIVE_PING: &[u8] = b"\r\n\r\n";
+
+/// CRLF keep-alive pong (RFC 5626 section 3.5.1)
+pub const KEEPALIVE_PONG: &[u8] = b"\r\n";
+
+/// Connection management configuration
+#[derive(Debug, Clone)]
+pub struct ConnectionConfig {
+    /// Close connections that have seen no traffic for this long
+    pub idle_timeout: Duration,
+    /// Maximum number of simultaneously open connections
+    pub max_connections: usize,
+    /// Interval at which idle connections are reaped
+    pub reap_interval: Duration,
+}
+
+impl Default for ConnectionConfig {
+    fn default() -> Self {
+        Self {
+            idle_timeout: Duration::from_secs(600),
+            max_connections: 1024,
+            reap_interval: Duration::from_secs(30),
+        }
+    }
+}
+
+/// Kind of keep-alive frame found in a read buffer
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum KeepAlive {
+    /// Double CRLF ping, must be answered with a pong
+    Ping,
+    /// Single CRLF pong, nothing to do
+    Pong,
+}
+
+/// Classify a buffer consisting solely of CRLF keep-alive frames
+///
+/// Returns `None` if the buffer contains anything other than CRLFs.
+pub fn classify_keepalive(data: &[u8]) -> Option<KeepAlive> {
+    if data.is_empty() || data.chunks(2).any(|c| c != b"\r\n") {
+        return None;
+    }
+
+    if data.len() >= KEEPALIVE_PING.len() {
+        Some(KeepAlive::Ping)
+    } else {
+        Some(KeepAlive::Pong)
+    }
+}
+
+/// An open stream connection
+#[derive(Debug, Clone)]
+struct Connection {
+    /// Writer channel feeding the connection's write half
+    writer: mpsc::Sender<Bytes>,
+    /// Reader and writer tasks owning the socket halves
+    tasks: Vec<AbortHandle>,
+    /// Last time data was read from or written to the connection
+    last_activity: Instant,
+    /// Dialogs (Call-IDs) currently using this connection
+    dialogs: HashSet<String>,
+}
+
+impl Connection {
+    /// Stop the connection's tasks, dropping (and so closing) the socket
+    fn close(&self) {
+        for task in &self.tasks {
+            task.abort();
+        }
+    }
+}
+
+/// Snapshot of a connection for diagnostics
+#[derive(Debug, Clone)]
+pub struct ConnectionInfo {
+    pub remote: SocketAddr,
+    pub idle_for: Duration,
+    pub active_dialogs: usize,
+}
+
+/// Table of open stream connections
+pub struct ConnectionTable {
+    config: ConnectionConfig,
+    connections: RwLock<HashMap<SocketAddr, Connection>>,
+    /// Alias (address advertised in Via/Contact) to actual remote address
+    aliases: RwLock<HashMap<SocketAddr, SocketAddr>>,
+}
+
+impl ConnectionTable {
+    pub fn new(config: ConnectionConfig) -> Self {
+        Self {
+            config,
+            connections: RwLock::new(HashMap::new()),
+            aliases: RwLock::new(HashMap::new()),
+        }
+    }
+
+    pub fn config(&self) -> &ConnectionConfig {
+        &self.config
+    }
+
+    /// Register a newly opened connection
+    ///
+    /// If the table is full, the least recently used connection without an
+    /// active dialog is evicted first. Returns false if no room could be made.
+    pub async fn insert(&self, remote: SocketAddr, writer: mpsc::Sender<Bytes>) -> bool {
+        let mut connections = self.connections.write().await;
+
+        if !connections.contains_key(&remote) && connections.len() >= self.config.max_connections {
+            let victim = connections
+                .iter()
+                .filter(|(_, c)| c.dialogs.is_empty())
+                .min_by_key(|(_, c)| c.last_activity)
+                .map(|(addr, _)| *addr);
+
+            match victim {
+                Some(addr) => {
+                    info!("Evicting least recently used connection to {}", addr);
+                    if let Some(evicted) = connections.remove(&addr) {
+                        evicted.close();
+                    }
+                    counter!("sip_tcp_connections_evicted_total").increment(1);
+                }
+                None => {
+                    warn!(
+                        "Connection limit ({}) reached and all connections carry dialogs, refusing {}",
+                        self.config.max_connections, remote
+                    );
+                    return false;
+                }
+            }
+        }
+
+        let replaced = connections.insert(
+            remote,
+            Connection {
+                writer,
+                tasks: Vec::new(),
+                last_activity: Instant::now(),
+                dialogs: HashSet::new(),
+            },
+        );
+        if let Some(replaced) = replaced {
+            replaced.close();
+        }
+        gauge!("sip_tcp_connections_open").set(connections.len() as f64);
+        debug!("Registered connection to {}", remote);
+        true
+    }
+
+    /// Hand the tasks serving the connection to `remote` to the table, which
+    /// aborts them when it closes the connection
+    ///
+    /// If the connection was evicted in the meantime, the tasks are aborted
+    /// right away and false is returned.
+    pub async fn set_tasks(&self, remote: &SocketAddr, tasks: Vec<AbortHandle>) -> bool {
+        match self.connections.write().await.get_mut(remote) {
+            Some(connection) => {
+                connection.tasks = tasks;
+                true
+            }
+            None => {
+                debug!("Connection to {} closed before its tasks started", remote);
+                for task in tasks {
+                    task.abort();
+                }
+                false
+            }
+        }
+    }
+
+    /// Remove a connection (closed by peer or on error)
+    pub async fn remove(&self, remote: &SocketAddr) {
+        let mut connections = self.connections.write().await;
+        let removed = connections.remove(remote);
+        if removed.is_some() {
+            gauge!("sip_tcp_connections_open").set(connections.len() as f64);
+            debug!("Removed connection to {}", remote);
+        }
+        drop(connections);
+
+        self.aliases.write().await.retain(|_, actual| actual != remote);
+
+        // Last: the caller may be one of the connection's own tasks
+        if let Some(connection) = removed {
+            connection.close();
+        }
+    }
+
+    /// Close every connection (their listener is going away)
+    pub async fn close_all(&self) {
+        let mut connections = self.connections.write().await;
+        if !connections.is_empty() {
+            info!("Closing {} connections", connections.len());
+        }
+        for (_, connection) in connections.drain() {
+            connection.close();
+        }
+        gauge!("sip_tcp_connections_open").set(0.0);
+        drop(connections);
+
+        self.aliases.write().await.clear();
+    }
+
+    /// Learn that `alias` (e.g. the sent-by of a Via or a Contact address)
+    /// is reachable through the connection from `remote`
+    ///
+    /// Anyone can put any address in a Via or Contact, so an alias learned
+    /// this way never replaces a mapping to a connection that is still open,
+    /// nor shadows an open connection to the alias itself. Use
+    /// [`bind_alias`](Self::bind_alias) once the sender is authenticated.
+    pub async fn add_alias(&self, alias: SocketAddr, remote: SocketAddr) {
+        if alias == remote {
+            return;
+        }
+        let connections = self.connections.read().await;
+        if connections.contains_key(&alias) {
+            return;
+        }
+        let mut aliases = self.aliases.write().await;
+        match aliases.get(&alias) {
+            Some(actual) if *actual == remote => {}
+            Some(actual) if connections.contains_key(actual) => {
+                debug!(
+                    "Not re-pointing connection alias {} from {} to {}",
+                    alias, actual, remote
+                );
+            }
+            _ => {
+                debug!("Learned connection alias {} -> {}", alias, remote);
+                aliases.insert(alias, remote);
+            }
+        }
+    }
+
+    /// Point `alias` at the connection from `remote`, replacing any earlier
+    /// mapping, for a sender that proved who it is (an authenticated
+    /// REGISTER)
+    pub async fn bind_alias(&self, alias: SocketAddr, remote: SocketAddr) {
+        if alias == remote {
+            return;
+        }
+        debug!("Bound connection alias {} -> {}", alias, remote);
+        self.aliases.write().await.insert(alias, remote);
+    }
+
+    /// Resolve an address to the remote address of an open connection
+    ///
+    /// An open connection to `addr` itself wins over an alias.
+    pub async fn resolve(&self, addr: &SocketAddr) -> Option<SocketAddr> {
+        let connections = self.connections.read().await;
+        if connections.contains_key(addr) {
+            return Some(*addr);
+        }
+
+        let remote = *self.aliases.read().await.get(addr)?;
+        connections.contains_key(&remote).then_some(remote)
+    }
+
+    /// Get the writer for an existing connection to `addr` (directly or via alias)
+    pub async fn writer_for(&self, addr: &SocketAddr) -> Option<mpsc::Sender<Bytes>> {
+        let remote = self.resolve(addr).await?;
+        let mut connections = self.connections.write().await;
+        let connection = connections.get_mut(&remote)?;
+
+        if connection.writer.is_closed() {
+            if let Some(connection) = connections.remove(&remote) {
+                connection.close();
+            }
+            gauge!("sip_tcp_connections_open").set(connections.len() as f64);
+            return None;
+        }
+
+        connection.last_activity = Instant::now();
+        counter!("sip_tcp_connection_reuse_total").increment(1);
+        Some(connection.writer.clone())
+    }
+
+    /// Record traffic on a connection
+    pub async fn touch(&self, remote: &SocketAddr) {
+        if let Some(connection) = self.connections.write().await.get_mut(remote) {
+            connection.last_activity = Instant::now();
+        }
+    }
+
+    /// Mark a dialog as using the connection to `addr`, protecting it from eviction
+    pub async fn attach_dialog(&self, addr: &SocketAddr, call_id: &str) {
+        if let Some(remote) = self.resolve(addr).await {
+            if let Some(connection) = self.connections.write().await.get_mut(&remote) {
+                connection.dialogs.insert(call_id.to_string());
+            }
+        }
+    }
+
+    /// Release a dialog from whichever connection carries it
+    pub async fn detach_dialog(&self, call_id: &str) {
+        for connection in self.connections.write().await.values_mut() {
+            connection.dialogs.remove(call_id);
+        }
+    }
+
+    /// Close connections idle for longer than the configured timeout
+    ///
+    /// Connections with active dialogs are kept. Returns the reaped addresses.
+    pub async fn reap_idle(&self) -> Vec<SocketAddr> {
+        let idle_timeout = self.config.idle_timeout;
+        let mut connections = self.connections.write().await;
+
+        let reaped: Vec<SocketAddr> = connections
+            .iter()
+            .filter(|(_, c)| c.dialogs.is_empty() && c.last_activity.elapsed() >= idle_timeout)
+            .map(|(addr, _)| *addr)
+            .collect();
+
+        for addr in &reaped {
+            info!("Closing idle connection to {}", addr);
+            if let Some(connection) = connections.remove(addr) {
+                connection.close();
+            }
+        }
+        gauge!("sip_tcp_connections_open").set(connections.len() as f64);
+        drop(connections);
+
+        if !reaped.is_empty() {
+            self.aliases
+                .write()
+                .await
+                .retain(|_, actual| !reaped.contains(actual));
+        }
+
+        reaped
+    }
+
+    /// Number of open connections
+    pub async fn len(&self) -> usize {
+        self.connections.read().await.len()
+    }
+
+    pub async fn is_empty(&self) -> bool {
+        self.connections.read().await.is_empty()
+    }
+
+    /// Diagnostic snapshot of all open connections
+    pub async fn snapshot(&self) -> Vec<ConnectionInfo> {
+        self.connections
+            .read()
+            .await
+            .iter()
+            .map(|(remote, c)| ConnectionInfo {
+                remote: *remote,
+                idle_for: c.last_activity.elapsed(),
+                active_dialogs: c.dialogs.len(),
+            })
+            .collect()
+    }
+
+    /// Spawn the periodic idle reaper
+    pub fn spawn_reaper(self: &Arc<Self>) -> tokio::task::JoinHandle<()> {
+        let table = Arc::downgrade(self);
+        let interval = self.config.reap_interval;
+        tokio::spawn(async move {
+            loop {
+                tokio::time::sleep(interval).await;
+                match table.upgrade() {
+                    Some(table) => {
+                        table.reap_idle().await;
+                    }
+                    None => break,
+                }
+            }
+        })
+    }
+}
+
+impl Default for ConnectionTable {
+    fn default() -> Self {
+        Self::new(ConnectionConfig::default())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn addr(port: u16) -> SocketAddr {
+        SocketAddr::from(([127, 0, 0, 1], port))
+    }
+
+    #[test]
+    fn test_classify_keepalive() {
+        assert_eq!(classify_keepalive(b"\r\n\r\n"), Some(KeepAlive::Ping));
+        assert_eq!(classify_keepalive(b"\r\n"), Some(KeepAlive::Pong));
+        assert_eq!(classify_keepalive(b""), None);
+        assert_eq!(classify_keepalive(b"REGISTER sip:a SIP/2.0\r\n"), None);
+    }
+
+    #[tokio::test]
+    async fn test_alias_resolution() {
+        let table = ConnectionTable::default();
+        let (tx, _rx) = mpsc::channel(8);
+
+        table.insert(addr(40000), tx).await;
+        table.add_alias(addr(5060), addr(40000)).await;
+
+        assert_eq!(table.resolve(&addr(5060)).await, Some(addr(40000)));
+        assert_eq!(table.resolve(&addr(40000)).await, Some(addr(40000)));
+        assert!(table.writer_for(&addr(5060)).await.is_some());
+
+        table.remove(&addr(40000)).await;
+        assert_eq!(table.resolve(&addr(5060)).await, None);
+    }
+
+    #[tokio::test]
+    async fn test_unauthenticated_alias_does_not_hijack_live_mapping() {
+        let table = ConnectionTable::default();
+        let (tx, _rx) = mpsc::channel(8);
+
+        table.insert(addr(40000), tx.clone()).await;
+        table.insert(addr(40001), tx.clone()).await;
+        table.insert(addr(6060), tx).await;
+        table.add_alias(addr(5060), addr(40000)).await;
+
+        // Another connection claiming the address is ignored...
+        table.add_alias(addr(5060), addr(40001)).await;
+        assert_eq!(table.resolve(&addr(5060)).await, Some(addr(40000)));
+        // ...as is one claiming the address of an open connection
+        table.add_alias(addr(6060), addr(40001)).await;
+        assert_eq!(table.resolve(&addr(6060)).await, Some(addr(6060)));
+
+        // An authenticated sender re-points it
+        table.bind_alias(addr(5060), addr(40001)).await;
+        assert_eq!(table.resolve(&addr(5060)).await, Some(addr(40001)));
+
+        // Once the connection is gone the alias may be learned again
+        table.remove(&addr(40001)).await;
+        table.add_alias(addr(5060), addr(40000)).await;
+        assert_eq!(table.resolve(&addr(5060)).await, Some(addr(40000)));
+    }
+
+    #[tokio::test]
+    async fn test_lru_eviction_skips_active_dialogs() {
+        let table = ConnectionTable::new(ConnectionConfig {
+            max_connections: 2,
+            ..Default::default()
+        });
+        let (tx, _rx) = mpsc::channel(8);
+
+        table.insert(addr(1), tx.clone()).await;
+        table.insert(addr(2), tx.clone()).await;
+        table.attach_dialog(&addr(1), "call-1").await;
+
+        // addr(1) is older but carries a dialog, so addr(2) gets evicted
+        assert!(table.insert(addr(3), tx.clone()).await);
+        assert!(table.resolve(&addr(1)).await.is_some());
+        assert!(table.resolve(&addr(2)).await.is_none());
+        assert!(table.resolve(&addr(3)).await.is_some());
+
+        // With every connection carrying a dialog, new connections are refused
+        table.attach_dialog(&addr(3), "call-3").await;
+        assert!(!table.insert(addr(4), tx.clone()).await);
+
+        table.detach_dialog("call-3").await;
+        assert!(table.insert(addr(4), tx).await);
+    }
+
+    #[tokio::test]
+    async fn test_reap_idle() {
+        let table = ConnectionTable::new(ConnectionConfig {
+            idle_timeout: Duration::from_millis(0),
+            ..Default::default()
+        });
+        let (tx, _rx) = mpsc::channel(8);
+
+        table.insert(addr(1), tx.clone()).await;
+        table.insert(addr(2), tx).await;
+        table.attach_dialog(&addr(2), "call-2").await;
+
+        let reaped = table.reap_idle().await;
+        assert_eq!(reaped, vec![addr(1)]);
+        assert_eq!(table.len().await, 1);
+    }
+}
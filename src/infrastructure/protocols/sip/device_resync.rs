@@ -0,0 +1,512 @@
+//! Check-sync NOTIFYs that make phones re-fetch their configuration
+//!
+//! Sends an unsolicited NOTIFY with the vendor's check-sync event to the
+//! registered contacts of a provisioned device. Contacts whose User-Agent
+//! carries the device's MAC address are preferred; phones that do not
+//! announce it get the NOTIFY on every contact of their user. A device
+//! that is not registered gets it when it next registers.
+//!
+//! Changes to provisioning data go through [`DeviceResyncer::schedule_user`],
+//! which waits for the changes to settle so a bulk edit sends one NOTIFY.
+
+use super::address::uri_socket_addr;
+use super::advertise::AddressAdvertiser;
+use super::registrar::{Binding, Registrar};
+use super::registration_events::{aor_user, RegistrationEventType};
+use super::transport::{OutgoingMessage, TransportProtocol};
+use crate::domain::device_provisioning::{
+    normalize_mac, DeviceProfile, DeviceResyncStatus, ProvisioningConfig,
+};
+use bytes::Bytes;
+use chrono::{DateTime, Utc};
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::sync::{broadcast, mpsc};
+use tokio::task::JoinHandle;
+use tracing::{debug, info, warn};
+use uuid::Uuid;
+
+/// Result of a resync request
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ResyncOutcome {
+    /// NOTIFY sent to this many contacts
+    Sent { contacts: usize },
+    /// Not registered; sent when the device registers
+    Queued,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DeviceResyncError {
+    UnknownDevice(String),
+}
+
+impl fmt::Display for DeviceResyncError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DeviceResyncError::UnknownDevice(mac) => write!(f, "Device {} not found", mac),
+        }
+    }
+}
+
+impl std::error::Error for DeviceResyncError {}
+
+#[derive(Debug, Default)]
+struct DeviceState {
+    last_resync_at: Option<DateTime<Utc>>,
+    last_resync_contacts: usize,
+    pending: bool,
+    last_config_fetch_at: Option<DateTime<Utc>>,
+    /// Bumped by every scheduled resync; only the latest one fires
+    generation: u64,
+}
+
+/// Sends check-sync NOTIFYs to provisioned devices
+pub struct DeviceResyncer {
+    registrar: Arc<Registrar>,
+    outbound: mpsc::Sender<OutgoingMessage>,
+    domain: String,
+    /// Address put in Via/Contact of our NOTIFYs
+    local_addr: String,
+    /// External address used instead of `local_addr` behind NAT
+    advertiser: Option<Arc<AddressAdvertiser>>,
+    /// Profiles by normalized MAC address
+    devices: HashMap<String, DeviceProfile>,
+    auto_resync: bool,
+    debounce: Duration,
+    state: Mutex<HashMap<String, DeviceState>>,
+    cseq: AtomicU32,
+}
+
+impl DeviceResyncer {
+    pub fn new(
+        registrar: Arc<Registrar>,
+        outbound: mpsc::Sender<OutgoingMessage>,
+        domain: String,
+        local_addr: String,
+        config: &ProvisioningConfig,
+    ) -> Self {
+        let devices = config
+            .devices
+            .iter()
+            .filter_map(|device| Some((normalize_mac(&device.mac)?, device.clone())))
+            .collect();
+        Self {
+            registrar,
+            outbound,
+            domain,
+            local_addr,
+            advertiser: None,
+            devices,
+            auto_resync: config.auto_resync,
+            debounce: Duration::from_secs(config.resync_debounce_secs),
+            state: Mutex::new(HashMap::new()),
+            cseq: AtomicU32::new(1),
+        }
+    }
+
+    /// Advertise the external address in Via/Contact to contacts outside
+    /// the local subnets
+    pub fn with_address_advertiser(mut self, advertiser: Arc<AddressAdvertiser>) -> Self {
+        self.advertiser = Some(advertiser);
+        self
+    }
+
+    /// Quiet period of scheduled resyncs, instead of `resync_debounce_secs`
+    pub fn with_debounce(mut self, debounce: Duration) -> Self {
+        self.debounce = debounce;
+        self
+    }
+
+    /// Via sent-by and Contact host:port for a NOTIFY to `target`
+    fn sent_by(&self, target: &str) -> String {
+        match &self.advertiser {
+            Some(advertiser) => advertiser
+                .advertise_host_port(&self.local_addr, uri_socket_addr(target).map(|a| a.ip())),
+            None => self.local_addr.clone(),
+        }
+    }
+
+    /// Profile of a device, by MAC address in any notation
+    pub fn device(&self, mac: &str) -> Option<&DeviceProfile> {
+        self.devices.get(&normalize_mac(mac)?)
+    }
+
+    /// Normalized MAC addresses of a user's devices
+    fn devices_of(&self, username: &str) -> Vec<String> {
+        self.devices
+            .iter()
+            .filter(|(_, device)| device.username == username)
+            .map(|(mac, _)| mac.clone())
+            .collect()
+    }
+
+    /// Resync state of a device
+    pub fn status(&self, mac: &str) -> Option<DeviceResyncStatus> {
+        let mac = normalize_mac(mac)?;
+        let device = self.devices.get(&mac)?;
+        let state = self.state.lock().unwrap();
+        let state = state.get(&mac);
+        let last_resync_at = state.and_then(|s| s.last_resync_at);
+        let last_config_fetch_at = state.and_then(|s| s.last_config_fetch_at);
+        Some(DeviceResyncStatus {
+            mac,
+            vendor: device.vendor,
+            username: device.username.clone(),
+            realm: device.realm.clone(),
+            last_resync_at,
+            last_resync_contacts: state.map_or(0, |s| s.last_resync_contacts),
+            resync_pending: state.is_some_and(|s| s.pending),
+            last_config_fetch_at,
+            refetched: matches!(
+                (last_resync_at, last_config_fetch_at),
+                (Some(resync), Some(fetch)) if fetch >= resync
+            ),
+        })
+    }
+
+    /// The device fetched its configuration
+    pub fn config_fetched(&self, mac: &str) {
+        if let Some(mac) = normalize_mac(mac) {
+            if self.devices.contains_key(&mac) {
+                self.state.lock().unwrap().entry(mac).or_default().last_config_fetch_at =
+                    Some(Utc::now());
+            }
+        }
+    }
+
+    /// Send the check-sync NOTIFY now, or queue it until the device registers
+    pub async fn resync(&self, mac: &str) -> Result<ResyncOutcome, DeviceResyncError> {
+        let (mac, device) = normalize_mac(mac)
+            .and_then(|mac| Some((mac.clone(), self.devices.get(&mac)?.clone())))
+            .ok_or_else(|| DeviceResyncError::UnknownDevice(mac.to_string()))?;
+
+        let contacts = self.contacts_for(&mac, &device).await;
+        let outcome = if contacts.is_empty() {
+            info!("Device {} is not registered; resync queued", mac);
+            ResyncOutcome::Queued
+        } else {
+            for contact in &contacts {
+                self.send(contact, self.build_notify(&device, contact));
+            }
+            info!("Resync sent to device {} ({} contacts)", mac, contacts.len());
+            ResyncOutcome::Sent {
+                contacts: contacts.len(),
+            }
+        };
+
+        let mut state = self.state.lock().unwrap();
+        let state = state.entry(mac).or_default();
+        match outcome {
+            ResyncOutcome::Queued => state.pending = true,
+            ResyncOutcome::Sent { contacts } => {
+                state.pending = false;
+                state.last_resync_at = Some(Utc::now());
+                state.last_resync_contacts = contacts;
+            }
+        }
+        Ok(outcome)
+    }
+
+    /// Provisioning data of a user changed; resync their devices once the
+    /// changes settle
+    pub fn schedule_user(self: &Arc<Self>, username: &str) {
+        if !self.auto_resync {
+            return;
+        }
+        for mac in self.devices_of(username) {
+            self.schedule(mac);
+        }
+    }
+
+    /// Provisioning data of everyone changed (company speed dials)
+    pub fn schedule_all(self: &Arc<Self>) {
+        if !self.auto_resync {
+            return;
+        }
+        let macs: Vec<String> = self.devices.keys().cloned().collect();
+        for mac in macs {
+            self.schedule(mac);
+        }
+    }
+
+    fn schedule(self: &Arc<Self>, mac: String) {
+        let generation = {
+            let mut state = self.state.lock().unwrap();
+            let state = state.entry(mac.clone()).or_default();
+            state.generation += 1;
+            state.generation
+        };
+        debug!("Resync of device {} scheduled", mac);
+
+        let resyncer = self.clone();
+        tokio::spawn(async move {
+            tokio::time::sleep(resyncer.debounce).await;
+            let latest = resyncer
+                .state
+                .lock()
+                .unwrap()
+                .get(&mac)
+                .is_some_and(|s| s.generation == generation);
+            if latest {
+                let _ = resyncer.resync(&mac).await;
+            }
+        });
+    }
+
+    /// Send queued resyncs as devices register
+    pub fn spawn_registration_listener(self: Arc<Self>) -> JoinHandle<()> {
+        let mut rx = self.registrar.subscribe_events();
+        tokio::spawn(async move {
+            loop {
+                match rx.recv().await {
+                    Ok(event) => {
+                        if !matches!(
+                            event.event_type,
+                            RegistrationEventType::Added | RegistrationEventType::Refreshed
+                        ) {
+                            continue;
+                        }
+                        let username = match aor_user(&event.aor) {
+                            Some(user) => user.to_string(),
+                            None => continue,
+                        };
+                        for mac in self.devices_of(&username) {
+                            let pending = self
+                                .state
+                                .lock()
+                                .unwrap()
+                                .get(&mac)
+                                .is_some_and(|s| s.pending);
+                            if pending {
+                                let _ = self.resync(&mac).await;
+                            }
+                        }
+                    }
+                    Err(broadcast::error::RecvError::Lagged(skipped)) => {
+                        warn!("Device resync missed {} registration events", skipped);
+                    }
+                    Err(broadcast::error::RecvError::Closed) => break,
+                }
+            }
+        })
+    }
+
+    /// Registered contacts of a device
+    async fn contacts_for(&self, mac: &str, device: &DeviceProfile) -> Vec<String> {
+        let bindings: Vec<Binding> = self
+            .registrar
+            .get_all_registrations()
+            .await
+            .into_iter()
+            .filter(|r| aor_user(&r.aor) == Some(device.username.as_str()))
+            .flat_map(|r| r.bindings)
+            .collect();
+
+        let announces_mac = |binding: &Binding| {
+            binding.user_agent.as_deref().is_some_and(|ua| {
+                ua.to_ascii_lowercase()
+                    .replace([':', '-'], "")
+                    .contains(mac)
+            })
+        };
+        let own: Vec<String> = bindings
+            .iter()
+            .filter(|b| announces_mac(b))
+            .map(|b| b.contact.clone())
+            .collect();
+        if own.is_empty() {
+            bindings.into_iter().map(|b| b.contact).collect()
+        } else {
+            own
+        }
+    }
+
+    fn build_notify(&self, device: &DeviceProfile, contact: &str) -> String {
+        let aor = format!("sip:{}@{}", device.username, device.realm);
+        format!(
+            "NOTIFY {target} SIP/2.0\r\n\
+             Via: SIP/2.0/UDP {local};branch=z9hG4bK{branch}\r\n\
+             Max-Forwards: 70\r\n\
+             From: <sip:{domain}>;tag={from_tag}\r\n\
+             To: <{aor}>\r\n\
+             Call-ID: {call_id}@{domain}\r\n\
+             CSeq: {cseq} NOTIFY\r\n\
+             Contact: <sip:{local}>\r\n\
+             Event: {event}\r\n\
+             Subscription-State: terminated\r\n\
+             Content-Length: 0\r\n\
+             \r\n",
+            target = contact,
+            local = self.sent_by(contact),
+            branch = Uuid::new_v4().simple(),
+            domain = self.domain,
+            from_tag = Uuid::new_v4().simple(),
+            aor = aor,
+            call_id = Uuid::new_v4(),
+            cseq = self.cseq.fetch_add(1, Ordering::Relaxed),
+            event = device.vendor.check_sync_event(),
+        )
+    }
+
+    fn send(&self, contact: &str, request: String) {
+        let destination = match uri_socket_addr(contact) {
+            Some(destination) => destination,
+            None => {
+                warn!("Cannot send resync NOTIFY to unresolvable contact {}", contact);
+                return;
+            }
+        };
+        let protocol = if contact.to_lowercase().contains("transport=tcp") {
+            TransportProtocol::Tcp
+        } else {
+            TransportProtocol::Udp
+        };
+
+        debug!("Sending resync NOTIFY to {} ({})", contact, destination);
+        if let Err(e) = self.outbound.try_send(OutgoingMessage {
+            data: Bytes::from(request),
+            destination,
+            protocol,
+        }) {
+            warn!("Failed to queue resync NOTIFY to {}: {}", contact, e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::domain::device_provisioning::DeviceVendor;
+    use crate::infrastructure::protocols::sip::message::SipRequest;
+
+    fn device(mac: &str, vendor: DeviceVendor, username: &str) -> DeviceProfile {
+        DeviceProfile {
+            mac: mac.to_string(),
+            vendor,
+            username: username.to_string(),
+            realm: "example.com".to_string(),
+        }
+    }
+
+    fn setup(debounce: Duration) -> (Arc<Registrar>, Arc<DeviceResyncer>, mpsc::Receiver<OutgoingMessage>) {
+        let registrar = Arc::new(Registrar::new());
+        let (tx, rx) = mpsc::channel(16);
+        let config = ProvisioningConfig {
+            devices: vec![
+                device("80:5e:c0:aa:bb:cc", DeviceVendor::Yealink, "alice"),
+                device("0004f2112233", DeviceVendor::Polycom, "bob"),
+                device("001122334455", DeviceVendor::Generic, "carol"),
+            ],
+            ..Default::default()
+        };
+        let resyncer = Arc::new(
+            DeviceResyncer::new(
+                registrar.clone(),
+                tx,
+                "example.com".to_string(),
+                "192.0.2.1:5060".to_string(),
+                &config,
+            )
+            .with_debounce(debounce),
+        );
+        (registrar, resyncer, rx)
+    }
+
+    fn event_header(message: &OutgoingMessage) -> String {
+        let text = String::from_utf8_lossy(&message.data).to_string();
+        text.lines()
+            .find_map(|line| line.strip_prefix("Event: "))
+            .unwrap()
+            .to_string()
+    }
+
+    #[tokio::test]
+    async fn test_notify_per_vendor() {
+        let (registrar, resyncer, mut rx) = setup(Duration::from_secs(5));
+        for (user, contact) in [
+            ("alice", "sip:alice@10.0.0.5:5060"),
+            ("bob", "sip:bob@10.0.0.6:5060"),
+            ("carol", "sip:carol@10.0.0.7:5060;transport=tcp"),
+        ] {
+            registrar
+                .add_binding(format!("sip:{}@example.com", user), contact.to_string(), 3600)
+                .await
+                .unwrap();
+        }
+
+        assert_eq!(
+            resyncer.resync("805EC0AABBCC").await,
+            Ok(ResyncOutcome::Sent { contacts: 1 })
+        );
+        let yealink = rx.try_recv().unwrap();
+        assert_eq!(yealink.destination, "10.0.0.5:5060".parse().unwrap());
+        assert_eq!(event_header(&yealink), "check-sync;reboot=false");
+        assert!(SipRequest::parse(&yealink.data).is_ok());
+
+        resyncer.resync("00:04:f2:11:22:33").await.unwrap();
+        assert_eq!(event_header(&rx.try_recv().unwrap()), "check-sync");
+
+        resyncer.resync("001122334455").await.unwrap();
+        let generic = rx.try_recv().unwrap();
+        assert_eq!(event_header(&generic), "check-sync;reboot=false");
+        assert_eq!(generic.protocol, TransportProtocol::Tcp);
+
+        assert_eq!(
+            resyncer.resync("aabbccddeeff").await,
+            Err(DeviceResyncError::UnknownDevice("aabbccddeeff".to_string()))
+        );
+    }
+
+    #[tokio::test]
+    async fn test_unregistered_device_resyncs_on_register() {
+        let (registrar, resyncer, mut rx) = setup(Duration::from_secs(5));
+        let listener = resyncer.clone().spawn_registration_listener();
+
+        assert_eq!(resyncer.resync("805ec0aabbcc").await, Ok(ResyncOutcome::Queued));
+        assert!(rx.try_recv().is_err());
+        assert!(resyncer.status("805ec0aabbcc").unwrap().resync_pending);
+
+        registrar
+            .add_binding("sip:alice@example.com".to_string(), "sip:alice@10.0.0.5:5060".to_string(), 3600)
+            .await
+            .unwrap();
+        let message = tokio::time::timeout(Duration::from_secs(1), rx.recv())
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(event_header(&message), "check-sync;reboot=false");
+
+        let status = resyncer.status("805ec0aabbcc").unwrap();
+        assert!(!status.resync_pending);
+        assert!(status.last_resync_at.is_some());
+        assert!(!status.refetched);
+
+        resyncer.config_fetched("80-5e-c0-aa-bb-cc");
+        assert!(resyncer.status("805ec0aabbcc").unwrap().refetched);
+        listener.abort();
+    }
+
+    #[tokio::test]
+    async fn test_bulk_changes_send_one_notify() {
+        let (registrar, resyncer, mut rx) = setup(Duration::from_millis(100));
+        registrar
+            .add_binding("sip:alice@example.com".to_string(), "sip:alice@10.0.0.5:5060".to_string(), 3600)
+            .await
+            .unwrap();
+
+        for _ in 0..5 {
+            resyncer.schedule_user("alice");
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+        resyncer.schedule_user("nobody");
+        assert!(rx.try_recv().is_err());
+
+        tokio::time::sleep(Duration::from_millis(300)).await;
+        assert!(rx.try_recv().is_ok());
+        assert!(rx.try_recv().is_err());
+        assert_eq!(resyncer.status("805ec0aabbcc").unwrap().last_resync_contacts, 1);
+    }
+}
@@ -1,6 +1,577 @@
 //! SIP dialog layer
 //!
 //! Manages SIP dialogs (call state)
+//!
+//! Currently tracks the offer/answer side of a dialog: a stable SDP origin,
+//! the last SDP sent and received, the answer to the last remote re-INVITE
+//! (so retransmissions get a byte-identical reply) and our own outstanding
+//! re-INVITE for glare detection (RFC 3261 sections 14.1 and 14.2).
+
+use super::call_state::CallLeg;
+use super::message::SipError;
+use super::sdp::{SdpOriginState, SdpSession};
+use crate::infrastructure::media::StreamDirection;
+use crate::infrastructure::sequence_vault::SequenceVault;
+use async_trait::async_trait;
+use rand::Rng;
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::RwLock;
+use tracing::{debug, info, warn};
+
+/// How to handle an incoming re-INVITE
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ReinviteAction {
+    /// A retransmission of an offer already answered; resend this SDP
+    Retransmission(String),
+    /// Our own re-INVITE is outstanding; reply 491 Request Pending
+    Glare,
+    /// A new offer to answer
+    NewOffer,
+}
+
+/// Result of a response to our own re-INVITE
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum LocalReinviteOutcome {
+    /// Provisional response, still waiting
+    Pending,
+    /// Offer accepted
+    Completed,
+    /// Glare: send the re-INVITE again after this delay
+    RetryAfter(Duration),
+    /// Rejected with a final error response
+    Failed(u16),
+}
+
+/// Times we offer a re-INVITE of our own before giving up on glare
+const MAX_REINVITE_ATTEMPTS: u32 = 5;
+
+/// Sends our re-INVITEs within a call's dialog
+#[async_trait]
+pub trait ReinviteSender: Send + Sync {
+    /// Send a re-INVITE offering `sdp` to the party on `leg`, returning the
+    /// final response status and its SDP body
+    async fn send_reinvite(
+        &self,
+        call_id: &str,
+        leg: &CallLeg,
+        cseq: u32,
+        sdp: &str,
+    ) -> Result<(u16, Option<String>), SipError>;
+}
+
+/// Random glare back-off from RFC 3261 section 14.1
+///
+/// The owner of the Call-ID waits 2.1 to 4 seconds, the other side 0 to 2
+/// seconds, both in units of 10 ms, so the non-owner usually retries first.
+pub fn glare_retry_delay(owns_call_id: bool) -> Duration {
+    let mut rng = rand::thread_rng();
+    let units: u64 = if owns_call_id {
+        rng.gen_range(210..=400)
+    } else {
+        rng.gen_range(0..=200)
+    };
+    Duration::from_millis(units * 10)
+}
+
+/// Offer/answer state of one dialog
+#[derive(Debug, Clone)]
+pub struct DialogMedia {
+    pub call_id: String,
+    /// Whether we generated the Call-ID (we sent the initial INVITE)
+    owns_call_id: bool,
+    origin: SdpOriginState,
+    local_hold: bool,
+    local_cseq: u32,
+    /// CSeq and SDP offer of our outstanding re-INVITE
+    pending_local: Option<(u32, String)>,
+    last_local_sdp: Option<String>,
+    last_remote_sdp: Option<String>,
+    last_answer: Option<(u32, String)>,
+    negotiated: Option<StreamDirection>,
+}
+
+impl DialogMedia {
+    pub fn new(call_id: &str, owns_call_id: bool) -> Self {
+        Self {
+            call_id: call_id.to_string(),
+            owns_call_id,
+            origin: SdpOriginState::new(),
+            local_hold: false,
+            local_cseq: 1,
+            pending_local: None,
+            last_local_sdp: None,
+            last_remote_sdp: None,
+            last_answer: None,
+            negotiated: None,
+        }
+    }
+
+    /// Whether we own the Call-ID
+    pub fn owns_call_id(&self) -> bool {
+        self.owns_call_id
+    }
+
+    /// Last SDP we sent (offer or answer)
+    pub fn last_local_sdp(&self) -> Option<&str> {
+        self.last_local_sdp.as_deref()
+    }
+
+    /// Last SDP we received (offer or answer)
+    pub fn last_remote_sdp(&self) -> Option<&str> {
+        self.last_remote_sdp.as_deref()
+    }
+
+    /// Whether we have a re-INVITE of our own outstanding
+    pub fn has_pending_reinvite(&self) -> bool {
+        self.pending_local.is_some()
+    }
+
+    /// Whether the local party wants the call on hold
+    pub fn is_local_hold(&self) -> bool {
+        self.local_hold
+    }
+
+    /// Set the local hold intent used for subsequent offers and answers
+    pub fn set_local_hold(&mut self, hold: bool) {
+        self.local_hold = hold;
+    }
+
+    /// Direction media currently flows, seen from our end
+    pub fn negotiated_direction(&self) -> Option<StreamDirection> {
+        self.negotiated
+    }
+
+    /// Decide how to handle an incoming re-INVITE with the given CSeq
+    pub fn classify_reinvite(&self, cseq: u32) -> ReinviteAction {
+        if let Some((answered_cseq, answer)) = &self.last_answer {
+            if *answered_cseq == cseq {
+                return ReinviteAction::Retransmission(answer.clone());
+            }
+        }
+
+        if self.pending_local.is_some() {
+            return ReinviteAction::Glare;
+        }
+
+        ReinviteAction::NewOffer
+    }
+
+    /// Answer a remote offer
+    ///
+    /// The answer direction mirrors the offer, restricted by our own hold
+    /// intent (a holding party does not receive media).
+    pub fn answer_offer(&mut self, cseq: u32, offer: &str, answer: SdpSession) -> String {
+        let offer_sdp = SdpSession::parse(offer);
+        let offered = offer_sdp
+            .as_ref()
+            .map(|sdp| sdp.audio_direction())
+            .unwrap_or(StreamDirection::SendRecv);
+
+        // Mirror the offer's m-lines, declining anything but audio
+        let mut answer = match &offer_sdp {
+            Some(offer_sdp) => answer.answer_to(offer_sdp),
+            None => answer,
+        };
+
+        let direction = self.local_direction(offered.reversed());
+        answer.set_direction(direction);
+        let body = self.origin.render(&mut answer);
+        self.negotiated = Some(direction);
+
+        self.last_remote_sdp = Some(offer.to_string());
+        self.last_local_sdp = Some(body.clone());
+        self.last_answer = Some((cseq, body.clone()));
+
+        debug!(
+            "Dialog {}: answered offer CSeq {} with {}",
+            self.call_id,
+            cseq,
+            direction.as_str()
+        );
+        body
+    }
+
+    /// CSeq of the last request we sent in the dialog
+    pub fn local_cseq(&self) -> u32 {
+        self.local_cseq
+    }
+
+    /// Continue our requests above `cseq`, e.g. the CSeq a previous
+    /// process reached
+    pub fn resume_cseq(&mut self, cseq: u32) {
+        self.local_cseq = self.local_cseq.max(cseq);
+    }
+
+    /// Start a re-INVITE of our own
+    ///
+    /// Returns the CSeq and SDP offer to send, or `None` if one is already
+    /// outstanding (RFC 3261 14.1 forbids a second one).
+    pub fn start_reinvite(&mut self, mut offer: SdpSession) -> Option<(u32, String)> {
+        if self.pending_local.is_some() {
+            return None;
+        }
+
+        offer.set_direction(self.local_direction(StreamDirection::SendRecv));
+        let body = self.origin.render(&mut offer);
+
+        self.local_cseq += 1;
+        self.pending_local = Some((self.local_cseq, body.clone()));
+        Some((self.local_cseq, body))
+    }
+
+    /// Handle a response to our outstanding re-INVITE
+    pub fn on_reinvite_response(
+        &mut self,
+        cseq: u32,
+        status: u16,
+        answer: Option<&str>,
+    ) -> LocalReinviteOutcome {
+        let offer = match &self.pending_local {
+            Some((pending_cseq, offer)) if *pending_cseq == cseq => offer.clone(),
+            _ => return LocalReinviteOutcome::Pending,
+        };
+
+        match status {
+            100..=199 => LocalReinviteOutcome::Pending,
+            200..=299 => {
+                self.pending_local = None;
+                // The answerer's direction, reversed, is what we ended up with
+                self.negotiated = answer
+                    .and_then(SdpSession::parse)
+                    .or_else(|| SdpSession::parse(&offer))
+                    .map(|sdp| match answer {
+                        Some(_) => sdp.audio_direction().reversed(),
+                        None => sdp.audio_direction(),
+                    });
+                self.last_local_sdp = Some(offer);
+                if let Some(answer) = answer {
+                    self.last_remote_sdp = Some(answer.to_string());
+                }
+                LocalReinviteOutcome::Completed
+            }
+            491 => {
+                self.pending_local = None;
+                let delay = glare_retry_delay(self.owns_call_id);
+                info!(
+                    "Dialog {}: re-INVITE glare, retrying in {:?}",
+                    self.call_id, delay
+                );
+                LocalReinviteOutcome::RetryAfter(delay)
+            }
+            _ => {
+                self.pending_local = None;
+                LocalReinviteOutcome::Failed(status)
+            }
+        }
+    }
+
+    fn local_direction(&self, wanted: StreamDirection) -> StreamDirection {
+        if !self.local_hold {
+            return wanted;
+        }
+        // Holding: keep sending (music on hold) but stop receiving
+        if wanted.sends() {
+            StreamDirection::SendOnly
+        } else {
+            StreamDirection::Inactive
+        }
+    }
+}
+
+/// Tracks dialog offer/answer state by Call-ID
+pub struct DialogManager {
+    dialogs: Arc<RwLock<HashMap<String, DialogMedia>>>,
+    /// Keeps our CSeqs across restarts
+    sequences: Option<Arc<SequenceVault>>,
+}
+
+impl DialogManager {
+    pub fn new() -> Self {
+        Self {
+            dialogs: Arc::new(RwLock::new(HashMap::new())),
+            sequences: None,
+        }
+    }
+
+    /// Record our CSeqs in `vault`, and continue dialogs above the CSeqs
+    /// it recovered
+    pub fn with_sequence_vault(mut self, vault: Arc<SequenceVault>) -> Self {
+        self.sequences = Some(vault);
+        self
+    }
+
+    /// Run `f` against the dialog for `call_id`, creating it if needed
+    pub async fn with_dialog<R>(
+        &self,
+        call_id: &str,
+        owns_call_id: bool,
+        f: impl FnOnce(&mut DialogMedia) -> R,
+    ) -> R {
+        let mut dialogs = self.dialogs.write().await;
+        let dialog = dialogs.entry(call_id.to_string()).or_insert_with(|| {
+            let mut dialog = DialogMedia::new(call_id, owns_call_id);
+            if let Some(cseq) = self.sequences.as_ref().and_then(|vault| vault.cseq(call_id)) {
+                dialog.resume_cseq(cseq);
+            }
+            dialog
+        });
+        let result = f(dialog);
+        if let Some(vault) = &self.sequences {
+            vault.observe_cseq(call_id, dialog.local_cseq());
+        }
+        result
+    }
+
+    /// Get a copy of the dialog state
+    pub async fn get(&self, call_id: &str) -> Option<DialogMedia> {
+        self.dialogs.read().await.get(call_id).cloned()
+    }
+
+    /// Forget a dialog
+    pub async fn remove(&self, call_id: &str) {
+        self.dialogs.write().await.remove(call_id);
+        if let Some(vault) = &self.sequences {
+            vault.forget(call_id);
+        }
+    }
+
+    /// Number of tracked dialogs
+    pub async fn count(&self) -> usize {
+        self.dialogs.read().await.len()
+    }
+
+    /// Re-offer our last SDP to `leg` with the current hold intent
+    ///
+    /// Returns `None` when the dialog is gone or no SDP has been exchanged on
+    /// it yet; see [`reoffer`](Self::reoffer) for glare handling.
+    pub async fn reinvite(
+        &self,
+        call_id: &str,
+        leg: &CallLeg,
+        sender: &dyn ReinviteSender,
+    ) -> Option<LocalReinviteOutcome> {
+        let offer = self
+            .dialogs
+            .read()
+            .await
+            .get(call_id)?
+            .last_local_sdp()
+            .and_then(SdpSession::parse)?;
+        self.reoffer(call_id, leg, sender, offer)
+            .await
+            .map(|(outcome, _)| outcome)
+    }
+
+    /// Offer `offer` to `leg` in a re-INVITE, returning the outcome and the
+    /// SDP answer
+    ///
+    /// On glare (a 491, or our own re-INVITE still outstanding) waits out the
+    /// RFC 3261 back-off and offers again, up to a few times. Returns `None`
+    /// when the dialog is gone.
+    pub async fn reoffer(
+        &self,
+        call_id: &str,
+        leg: &CallLeg,
+        sender: &dyn ReinviteSender,
+        offer: SdpSession,
+    ) -> Option<(LocalReinviteOutcome, Option<String>)> {
+        let mut outcome = LocalReinviteOutcome::Pending;
+        for _ in 0..MAX_REINVITE_ATTEMPTS {
+            let started = {
+                let mut dialogs = self.dialogs.write().await;
+                let dialog = dialogs.get_mut(call_id)?;
+                dialog
+                    .start_reinvite(offer.clone())
+                    .ok_or_else(|| glare_retry_delay(dialog.owns_call_id()))
+            };
+            let (cseq, body) = match started {
+                Ok(started) => started,
+                Err(delay) => {
+                    debug!(
+                        "Dialog {}: re-INVITE outstanding, offering again in {:?}",
+                        call_id, delay
+                    );
+                    outcome = LocalReinviteOutcome::RetryAfter(delay);
+                    tokio::time::sleep(delay).await;
+                    continue;
+                }
+            };
+
+            let (status, answer) = match sender.send_reinvite(call_id, leg, cseq, &body).await {
+                Ok(response) => response,
+                Err(e) => {
+                    warn!("Dialog {}: re-INVITE failed: {}", call_id, e);
+                    (408, None)
+                }
+            };
+
+            outcome = self
+                .dialogs
+                .write()
+                .await
+                .get_mut(call_id)?
+                .on_reinvite_response(cseq, status, answer.as_deref());
+            match outcome {
+                LocalReinviteOutcome::RetryAfter(delay) => tokio::time::sleep(delay).await,
+                _ => return Some((outcome, answer)),
+            }
+        }
+        Some((outcome, None))
+    }
+}
+
+impl Default for DialogManager {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::IpAddr;
+
+    fn local_sdp(ip: &str) -> SdpSession {
+        let ip: IpAddr = ip.parse().unwrap();
+        SdpSession::create_audio_session(ip, 20000)
+    }
+
+    /// Run one complete re-INVITE transaction from `offerer` to `answerer`
+    fn exchange(offerer: &mut DialogMedia, answerer: &mut DialogMedia, ip: &str, peer_ip: &str) {
+        let (cseq, offer) = offerer.start_reinvite(local_sdp(ip)).unwrap();
+        assert_eq!(answerer.classify_reinvite(cseq), ReinviteAction::NewOffer);
+        let answer = answerer.answer_offer(cseq, &offer, local_sdp(peer_ip));
+        assert_eq!(
+            offerer.on_reinvite_response(cseq, 200, Some(&answer)),
+            LocalReinviteOutcome::Completed
+        );
+    }
+
+    /// Both sides send a re-INVITE at the same time and resolve the glare
+    fn simulate_glare(a: &mut DialogMedia, b: &mut DialogMedia) {
+        let (a_cseq, _) = a.start_reinvite(local_sdp("10.0.0.1")).unwrap();
+        let (b_cseq, _) = b.start_reinvite(local_sdp("10.0.0.2")).unwrap();
+
+        // Each side sees the other's INVITE while its own is outstanding
+        assert_eq!(a.classify_reinvite(b_cseq), ReinviteAction::Glare);
+        assert_eq!(b.classify_reinvite(a_cseq), ReinviteAction::Glare);
+
+        let a_delay = match a.on_reinvite_response(a_cseq, 491, None) {
+            LocalReinviteOutcome::RetryAfter(d) => d,
+            other => panic!("unexpected outcome {:?}", other),
+        };
+        let b_delay = match b.on_reinvite_response(b_cseq, 491, None) {
+            LocalReinviteOutcome::RetryAfter(d) => d,
+            other => panic!("unexpected outcome {:?}", other),
+        };
+
+        // Retry in timer order
+        if a_delay <= b_delay {
+            exchange(a, b, "10.0.0.1", "10.0.0.2");
+            exchange(b, a, "10.0.0.2", "10.0.0.1");
+        } else {
+            exchange(b, a, "10.0.0.2", "10.0.0.1");
+            exchange(a, b, "10.0.0.1", "10.0.0.2");
+        }
+    }
+
+    #[test]
+    fn test_glare_retry_delay_ranges() {
+        for _ in 0..100 {
+            let owner = glare_retry_delay(true);
+            assert!(owner >= Duration::from_millis(2100) && owner <= Duration::from_secs(4));
+
+            let other = glare_retry_delay(false);
+            assert!(other <= Duration::from_secs(2));
+        }
+    }
+
+    #[test]
+    fn test_retransmitted_reinvite_gets_identical_answer() {
+        let mut dialog = DialogMedia::new("call-1", false);
+        let offer = local_sdp("10.0.0.2").to_string();
+
+        assert_eq!(dialog.classify_reinvite(2), ReinviteAction::NewOffer);
+        let answer = dialog.answer_offer(2, &offer, local_sdp("10.0.0.1"));
+
+        assert_eq!(
+            dialog.classify_reinvite(2),
+            ReinviteAction::Retransmission(answer.clone())
+        );
+
+        // A new, unchanged offer gets the same o= line (no version bump)
+        let again = dialog.answer_offer(3, &offer, local_sdp("10.0.0.1"));
+        assert_eq!(again, answer);
+    }
+
+    #[test]
+    fn test_simultaneous_hold_converges() {
+        let mut a = DialogMedia::new("call-glare", true);
+        let mut b = DialogMedia::new("call-glare", false);
+        a.set_local_hold(true);
+        b.set_local_hold(true);
+
+        simulate_glare(&mut a, &mut b);
+
+        assert_eq!(a.negotiated_direction(), Some(StreamDirection::Inactive));
+        assert_eq!(
+            a.negotiated_direction().map(|d| d.reversed()),
+            b.negotiated_direction()
+        );
+        assert!(!a.has_pending_reinvite() && !b.has_pending_reinvite());
+    }
+
+    #[test]
+    fn test_hold_and_resume_glare_converges() {
+        let mut a = DialogMedia::new("call-glare-2", true);
+        let mut b = DialogMedia::new("call-glare-2", false);
+
+        // A puts the call on hold while B refreshes the session
+        a.set_local_hold(true);
+        simulate_glare(&mut a, &mut b);
+
+        assert_eq!(a.negotiated_direction(), Some(StreamDirection::SendOnly));
+        assert_eq!(b.negotiated_direction(), Some(StreamDirection::RecvOnly));
+
+        // Both resume at once
+        a.set_local_hold(false);
+        simulate_glare(&mut a, &mut b);
+
+        assert_eq!(a.negotiated_direction(), Some(StreamDirection::SendRecv));
+        assert_eq!(b.negotiated_direction(), Some(StreamDirection::SendRecv));
+    }
+
+    #[tokio::test]
+    async fn test_dialog_manager() {
+        let manager = DialogManager::new();
+        manager
+            .with_dialog("call-1", false, |d| d.set_local_hold(true))
+            .await;
+
+        assert!(manager.get("call-1").await.unwrap().is_local_hold());
+        assert_eq!(manager.count().await, 1);
+
+        manager.remove("call-1").await;
+        assert!(manager.get("call-1").await.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_dialog_continues_above_recovered_cseq() {
+        let before = SequenceVault::new(100);
+        before.observe_cseq("call-1", 7);
+        let vault = Arc::new(SequenceVault::recovered(before.snapshot(), 100));
+        let manager = DialogManager::new().with_sequence_vault(vault.clone());
+
+        let (cseq, _) = manager
+            .with_dialog("call-1", true, |d| d.start_reinvite(local_sdp("10.0.0.1")))
+            .await
+            .unwrap();
+        assert_eq!(cseq, 108);
+        assert_eq!(vault.cseq("call-1"), Some(108));
 
-// Placeholder for dialog layer
-// TODO: Implement dialog management
+        manager.remove("call-1").await;
+        assert_eq!(vault.cseq("call-1"), None);
+    }
+}
@@ -1,7 +1,20 @@
 //! SIP message handlers
+//!
+//! Handlers are transaction users (RFC 3261 Section 8.2): they decide what
+//! to answer, and the server owns sending. A [`SipHandler`] answers each
+//! request with one final response. A [`TransactionUser`] gets a
+//! [`TransactionHandle`] instead, through which it sends any number of
+//! provisional responses and, possibly much later, the final one.
 
+use super::builder::ResponseBuilder;
 use super::message::{SipError, SipMethod, SipRequest, SipResponse};
 use async_trait::async_trait;
+use std::future::Future;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::mpsc;
+use tracing::{debug, warn};
 
 /// Trait for handling SIP requests
 #[async_trait]
@@ -12,3 +25,167 @@ pub trait SipHandler: Send + Sync {
     /// Check if this handler can handle the given method
     fn can_handle(&self, method: SipMethod) -> bool;
 }
+
+/// What the server does with a transaction once its user returns
+#[derive(Debug)]
+pub enum TransactionAction {
+    /// Send this final response
+    Respond(SipResponse),
+    /// The final response follows through the handle, e.g. once the
+    /// callee answered
+    Defer,
+    /// Nothing to send (ACK)
+    Absorb,
+}
+
+/// Handler of server transactions that answers in several steps
+#[async_trait]
+pub trait TransactionUser: Send + Sync {
+    /// Handle the request of `transaction`
+    ///
+    /// Responses sent through the handle go out right away, while the
+    /// handler keeps running. An error is answered with 500 unless a final
+    /// response was already sent.
+    async fn handle_transaction(
+        self: Arc<Self>,
+        transaction: TransactionHandle,
+    ) -> Result<TransactionAction, SipError>;
+
+    /// Check if this handler can handle the given method
+    fn can_handle(&self, method: SipMethod) -> bool;
+}
+
+/// Runs a [`SipHandler`] as a transaction user: its response is the
+/// transaction's final response
+pub struct SimpleTransactionUser {
+    handler: Arc<dyn SipHandler>,
+}
+
+impl SimpleTransactionUser {
+    pub fn new(handler: Arc<dyn SipHandler>) -> Self {
+        Self { handler }
+    }
+}
+
+#[async_trait]
+impl TransactionUser for SimpleTransactionUser {
+    async fn handle_transaction(
+        self: Arc<Self>,
+        transaction: TransactionHandle,
+    ) -> Result<TransactionAction, SipError> {
+        let response = self
+            .handler
+            .handle_request(transaction.request().clone())
+            .await?;
+        Ok(TransactionAction::Respond(response))
+    }
+
+    fn can_handle(&self, method: SipMethod) -> bool {
+        self.handler.can_handle(method)
+    }
+}
+
+/// A transaction user's side of a server transaction
+///
+/// Clones share the transaction; the server transmits what they send until
+/// the final response, then refuses further responses. The transaction
+/// ends without a final response when every clone is dropped before
+/// sending one.
+#[derive(Clone)]
+pub struct TransactionHandle {
+    request: Arc<SipRequest>,
+    responses: mpsc::UnboundedSender<SipResponse>,
+    completed: Arc<AtomicBool>,
+}
+
+impl TransactionHandle {
+    /// Handle of the transaction of `request`, with the receiver its
+    /// responses are to be transmitted from
+    pub fn new(request: SipRequest) -> (Self, mpsc::UnboundedReceiver<SipResponse>) {
+        let (responses, receiver) = mpsc::unbounded_channel();
+        let handle = Self {
+            request: Arc::new(request),
+            responses,
+            completed: Arc::new(AtomicBool::new(false)),
+        };
+        (handle, receiver)
+    }
+
+    pub fn request(&self) -> &SipRequest {
+        &self.request
+    }
+
+    /// Whether the final response was sent
+    pub fn is_completed(&self) -> bool {
+        self.completed.load(Ordering::SeqCst)
+    }
+
+    /// Send a provisional (1xx) response
+    pub fn send_provisional(&self, response: SipResponse) -> Result<(), SipError> {
+        if response.status_code() >= 200 {
+            return Err(SipError::TransactionError(format!(
+                "{} is not a provisional response",
+                response.status_code()
+            )));
+        }
+        if self.is_completed() {
+            return Err(SipError::TransactionError(
+                "Transaction already completed".to_string(),
+            ));
+        }
+        self.transmit(response)
+    }
+
+    /// Send the final (2xx-6xx) response, completing the transaction
+    pub fn respond(&self, response: SipResponse) -> Result<(), SipError> {
+        if response.status_code() < 200 {
+            return Err(SipError::TransactionError(format!(
+                "{} is not a final response",
+                response.status_code()
+            )));
+        }
+        if self.completed.swap(true, Ordering::SeqCst) {
+            return Err(SipError::TransactionError(
+                "Transaction already completed".to_string(),
+            ));
+        }
+        self.transmit(response)
+    }
+
+    /// Send the final response `event` yields, or the one `on_timeout`
+    /// yields when `event` takes longer than `timeout`
+    ///
+    /// Returns at once; an error of either is answered with 500.
+    pub fn respond_when<E, T>(self, event: E, timeout: Duration, on_timeout: T) -> TransactionAction
+    where
+        E: Future<Output = Result<SipResponse, SipError>> + Send + 'static,
+        T: Future<Output = Result<SipResponse, SipError>> + Send + 'static,
+    {
+        tokio::spawn(async move {
+            let response = match tokio::time::timeout(timeout, event).await {
+                Ok(response) => response,
+                Err(_) => {
+                    debug!("No event for {:?} within {:?}", self.request.call_id(), timeout);
+                    on_timeout.await
+                }
+            };
+            if let Err(e) = response.and_then(|response| self.respond(response)) {
+                warn!("Deferred response to {:?} failed: {}", self.request.call_id(), e);
+                if !self.is_completed() {
+                    if let Ok(error) =
+                        ResponseBuilder::server_internal_error().build_for_request(&self.request)
+                    {
+                        let _ = self.respond(error);
+                    }
+                }
+            }
+        });
+        TransactionAction::Defer
+    }
+
+    fn transmit(&self, response: SipResponse) -> Result<(), SipError> {
+        self.responses
+            .send(response)
+            .map_err(|_| SipError::TransactionError("Transaction ended".to_string()))
+    }
+}
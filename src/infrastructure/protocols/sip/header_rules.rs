@@ -0,0 +1,929 @@
+//! Per-trunk and per-route SIP header manipulation
+//!
+//! Header rules are attached to trunks (matched on their hosts) and to
+//! dial-plan routes (matched on the dialed number). Each is an ordered list
+//! of `add` (a header whose value may use template variables such as
+//! `${caller_ext}`, `${tenant}` or `${cdr_id}`), `remove` (headers by name
+//! or `*` pattern) and, for outbound rules, `preserve`. Inbound rules run on
+//! requests from the trunk or to the route right after parsing; outbound
+//! rules run on forwarded INVITEs just before they are sent.
+//!
+//! As a B2BUA the PBX does not pass on headers it does not know: they are
+//! dropped from forwarded INVITEs unless an outbound `preserve` rule lets
+//! them through. Headers carrying dialog and transaction state (Via, CSeq,
+//! Call-ID, ...) are protected; rules adding or removing them are rejected
+//! when the rules are loaded.
+
+use super::message::{SipError, SipRequest};
+use super::redirect::{uri_host, wildcard_match};
+use crate::domain::routing::lint::{DialRule, EntityKind, Lint, RoutingInventory};
+use crate::domain::routing::simulation::{Explain, Explanation, RoutedCall};
+use crate::domain::tenant_branding::realm_of;
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::net::IpAddr;
+use tracing::debug;
+use uuid::Uuid;
+
+/// Headers no rule may add or remove
+pub const PROTECTED_HEADERS: &[&str] = &[
+    "Via",
+    "From",
+    "To",
+    "Call-ID",
+    "CSeq",
+    "Max-Forwards",
+    "Contact",
+    "Route",
+    "Record-Route",
+    "Content-Length",
+    "Content-Type",
+];
+
+/// Compact forms (RFC 3261 7.3.3) of the protected headers
+const COMPACT_FORMS: &[(&str, &str)] = &[
+    ("v", "Via"),
+    ("f", "From"),
+    ("t", "To"),
+    ("i", "Call-ID"),
+    ("m", "Contact"),
+    ("l", "Content-Length"),
+    ("c", "Content-Type"),
+];
+
+/// Headers passed on to forwarded INVITEs without a `preserve` rule
+const KNOWN_HEADERS: &[&str] = &[
+    "Accept",
+    "Accept-Encoding",
+    "Accept-Language",
+    "Alert-Info",
+    "Allow",
+    "Allow-Events",
+    "Call-Info",
+    "Content-Disposition",
+    "Content-Encoding",
+    "Content-Language",
+    "Date",
+    "Expires",
+    "Min-SE",
+    "MIME-Version",
+    "Organization",
+    "Priority",
+    "Privacy",
+    "Proxy-Require",
+    "Reason",
+    "Require",
+    "Session-Expires",
+    "Subject",
+    "Supported",
+    "Timestamp",
+    "User-Agent",
+];
+
+/// Variables available in `add` templates as `${name}`
+pub const TEMPLATE_VARIABLES: &[&str] = &[
+    "caller_ext",
+    "caller_uri",
+    "callee",
+    "tenant",
+    "cdr_id",
+    "call_id",
+    "trunk",
+];
+
+/// One header manipulation
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "action", rename_all = "lowercase")]
+pub enum HeaderRule {
+    /// Append a header; copies already present are kept (remove them first
+    /// to replace them)
+    Add { name: String, value: String },
+    /// Remove headers whose name matches (`*` wildcard, case-insensitive)
+    Remove { name: String },
+    /// Pass the caller's headers matching `name` on to the forwarded INVITE
+    Preserve { name: String },
+}
+
+/// Rules of a trunk or route, applied in order
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct HeaderRules {
+    /// Applied to requests received from the trunk or for the route
+    pub inbound: Vec<HeaderRule>,
+    /// Applied to INVITEs forwarded to the trunk or the route
+    pub outbound: Vec<HeaderRule>,
+}
+
+/// Header rules of a trunk
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct TrunkHeaderRules {
+    /// Addresses and host names of the trunk: requests from them and
+    /// INVITEs forwarded to them get its rules
+    pub hosts: Vec<String>,
+    #[serde(flatten)]
+    pub rules: HeaderRules,
+}
+
+/// Header rules of a dial-plan route
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct RouteHeaderRules {
+    pub name: String,
+    /// Dialed numbers of the route (`*` wildcard), e.g. `00*`
+    pub pattern: String,
+    #[serde(flatten)]
+    pub rules: HeaderRules,
+}
+
+/// Header rules of all trunks and routes
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct HeaderRulesConfig {
+    /// Drop headers the PBX does not know from forwarded INVITEs
+    pub strip_unknown: bool,
+    /// Rules by trunk name
+    pub trunks: HashMap<String, TrunkHeaderRules>,
+    /// Routes, the first matching the dialed number applies
+    pub routes: Vec<RouteHeaderRules>,
+}
+
+impl Default for HeaderRulesConfig {
+    fn default() -> Self {
+        Self {
+            strip_unknown: true,
+            trunks: HashMap::new(),
+            routes: Vec::new(),
+        }
+    }
+}
+
+/// Why header rules were rejected
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum HeaderRuleError {
+    /// The rule adds or removes a protected header
+    Protected { rule: String, header: String },
+    /// Not a valid header name
+    InvalidName(String),
+    /// A header value spanning lines
+    InvalidValue(String),
+    /// The template uses an unknown variable
+    UnknownVariable(String),
+    /// A `${` without its closing `}`
+    UnterminatedVariable(String),
+    /// `preserve` in inbound rules
+    PreserveInbound(String),
+}
+
+impl std::fmt::Display for HeaderRuleError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            HeaderRuleError::Protected { rule, header } => {
+                write!(f, "rule {} would change protected header {}", rule, header)
+            }
+            HeaderRuleError::InvalidName(name) => write!(f, "invalid header name: {}", name),
+            HeaderRuleError::InvalidValue(value) => write!(f, "invalid header value: {:?}", value),
+            HeaderRuleError::UnknownVariable(name) => {
+                write!(f, "unknown template variable: {}", name)
+            }
+            HeaderRuleError::UnterminatedVariable(template) => {
+                write!(f, "unterminated variable in template: {}", template)
+            }
+            HeaderRuleError::PreserveInbound(name) => {
+                write!(f, "preserve {} only applies to outbound rules", name)
+            }
+        }
+    }
+}
+
+impl HeaderRule {
+    /// Name or pattern the rule applies to
+    pub fn name(&self) -> &str {
+        match self {
+            HeaderRule::Add { name, .. }
+            | HeaderRule::Remove { name }
+            | HeaderRule::Preserve { name } => name,
+        }
+    }
+
+    fn describe(&self) -> String {
+        match self {
+            HeaderRule::Add { name, .. } => format!("add {}", name),
+            HeaderRule::Remove { name } => format!("remove {}", name),
+            HeaderRule::Preserve { name } => format!("preserve {}", name),
+        }
+    }
+
+    /// Reject rules touching protected headers, bad names and templates
+    pub fn validate(&self) -> Result<(), HeaderRuleError> {
+        let name = self.name();
+        let valid_char = |c: char| c.is_ascii_graphic() && c != ':';
+        if name.is_empty() || !name.chars().all(valid_char) {
+            return Err(HeaderRuleError::InvalidName(name.to_string()));
+        }
+        match self {
+            HeaderRule::Add { value, .. } => {
+                if name.contains('*') {
+                    return Err(HeaderRuleError::InvalidName(name.to_string()));
+                }
+                if let Some(header) = protected_name(name) {
+                    return Err(self.protected(header));
+                }
+                if value.contains(['\r', '\n']) {
+                    return Err(HeaderRuleError::InvalidValue(value.clone()));
+                }
+                for variable in template_variables(value)? {
+                    if !TEMPLATE_VARIABLES.contains(&variable) {
+                        return Err(HeaderRuleError::UnknownVariable(variable.to_string()));
+                    }
+                }
+            }
+            HeaderRule::Remove { name } => {
+                // A pattern may not match any protected header either
+                let pattern = name.to_ascii_lowercase();
+                let compact = COMPACT_FORMS.iter().map(|(compact, _)| *compact);
+                let matched = PROTECTED_HEADERS
+                    .iter()
+                    .copied()
+                    .chain(compact)
+                    .find(|header| wildcard_match(&pattern, &header.to_ascii_lowercase()));
+                if let Some(header) = matched {
+                    return Err(self.protected(protected_name(header).unwrap_or(header)));
+                }
+            }
+            HeaderRule::Preserve { .. } => {}
+        }
+        Ok(())
+    }
+
+    fn protected(&self, header: &str) -> HeaderRuleError {
+        HeaderRuleError::Protected {
+            rule: self.describe(),
+            header: header.to_string(),
+        }
+    }
+}
+
+impl HeaderRules {
+    pub fn validate(&self) -> Result<(), HeaderRuleError> {
+        for rule in &self.inbound {
+            if let HeaderRule::Preserve { name } = rule {
+                return Err(HeaderRuleError::PreserveInbound(name.clone()));
+            }
+            rule.validate()?;
+        }
+        self.outbound.iter().try_for_each(HeaderRule::validate)
+    }
+
+    fn for_direction(&self, direction: HeaderDirection) -> &[HeaderRule] {
+        match direction {
+            HeaderDirection::Inbound => &self.inbound,
+            HeaderDirection::Outbound => &self.outbound,
+        }
+    }
+}
+
+impl HeaderRulesConfig {
+    pub fn validate(&self) -> Result<(), HeaderRuleError> {
+        self.trunks
+            .values()
+            .map(|trunk| &trunk.rules)
+            .chain(self.routes.iter().map(|route| &route.rules))
+            .try_for_each(HeaderRules::validate)
+    }
+}
+
+/// Values of the template variables for one call
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct HeaderContext {
+    pub caller_ext: Option<String>,
+    pub caller_uri: Option<String>,
+    /// Dialed number
+    pub callee: Option<String>,
+    /// Caller's realm
+    pub tenant: Option<String>,
+    pub cdr_id: Option<Uuid>,
+    pub call_id: Option<String>,
+    pub trunk: Option<String>,
+}
+
+impl HeaderContext {
+    /// Context of the call `request` sets up (no CDR or trunk yet)
+    pub fn from_request(request: &SipRequest) -> Self {
+        let (_, headers, _) = split_request(request);
+        let caller_uri = headers
+            .iter()
+            .find(|(name, _)| name.eq_ignore_ascii_case("From") || name.eq_ignore_ascii_case("f"))
+            .map(|(_, value)| addr_uri(value).to_string());
+        Self {
+            caller_ext: caller_uri.as_deref().and_then(uri_user).map(str::to_string),
+            tenant: caller_uri.as_deref().and_then(realm_of).map(str::to_string),
+            caller_uri,
+            callee: uri_user(&request.uri().to_string()).map(str::to_string),
+            cdr_id: None,
+            call_id: request.call_id(),
+            trunk: None,
+        }
+    }
+
+    pub fn with_cdr_id(mut self, cdr_id: Uuid) -> Self {
+        self.cdr_id = Some(cdr_id);
+        self
+    }
+
+    fn variable(&self, name: &str) -> Option<String> {
+        match name {
+            "caller_ext" => self.caller_ext.clone(),
+            "caller_uri" => self.caller_uri.clone(),
+            "callee" => self.callee.clone(),
+            "tenant" => self.tenant.clone(),
+            "cdr_id" => self.cdr_id.map(|id| id.to_string()),
+            "call_id" => self.call_id.clone(),
+            "trunk" => self.trunk.clone(),
+            _ => None,
+        }
+    }
+
+    /// `template` with its variables replaced; unset variables expand to
+    /// nothing
+    pub fn expand(&self, template: &str) -> String {
+        let mut expanded = String::with_capacity(template.len());
+        let mut rest = template;
+        while let Some(start) = rest.find("${") {
+            expanded.push_str(&rest[..start]);
+            let Some(end) = rest[start..].find('}') else {
+                break;
+            };
+            let name = &rest[start + 2..start + end];
+            expanded.push_str(&self.variable(name).unwrap_or_default());
+            rest = &rest[start + end + 1..];
+        }
+        expanded.push_str(rest);
+        expanded
+    }
+}
+
+/// Which way a request crosses the PBX
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum HeaderDirection {
+    /// Received from a trunk or for a route
+    Inbound,
+    /// Forwarded to a trunk or route
+    Outbound,
+}
+
+/// Applies the configured header rules
+pub struct HeaderRulesEngine {
+    config: HeaderRulesConfig,
+}
+
+impl HeaderRulesEngine {
+    pub fn new(config: HeaderRulesConfig) -> Result<Self, HeaderRuleError> {
+        config.validate()?;
+        Ok(Self { config })
+    }
+
+    pub fn config(&self) -> &HeaderRulesConfig {
+        &self.config
+    }
+
+    /// Name of the trunk whose hosts include `host`
+    pub fn trunk_of(&self, host: &str) -> Option<&str> {
+        self.config
+            .trunks
+            .iter()
+            .find(|(_, trunk)| trunk.hosts.iter().any(|h| h.eq_ignore_ascii_case(host)))
+            .map(|(name, _)| name.as_str())
+    }
+
+    /// Rules for a request from or to trunk host `host` for `request_uri`:
+    /// the trunk's first, then those of the first matching route
+    fn rules(&self, direction: HeaderDirection, host: &str, request_uri: &str) -> Vec<&HeaderRule> {
+        let mut rules = Vec::new();
+        if let Some(trunk) = self.trunk_of(host) {
+            rules.extend(self.config.trunks[trunk].rules.for_direction(direction));
+        }
+        if let Some(route) = self.route_for(request_uri) {
+            rules.extend(route.rules.for_direction(direction));
+        }
+        rules
+    }
+
+    /// First route matching the number dialed in `request_uri`
+    pub fn route_for(&self, request_uri: &str) -> Option<&RouteHeaderRules> {
+        let number = uri_user(request_uri)?;
+        self.config
+            .routes
+            .iter()
+            .find(|route| wildcard_match(&route.pattern, number))
+    }
+
+    /// Apply the inbound rules of the trunk at `source` and of the route
+    /// dialed to a request just received
+    pub fn apply_inbound(&self, request: &mut SipRequest, source: IpAddr) {
+        let source = source.to_string();
+        let request_uri = request.uri().to_string();
+        let rules = self.rules(HeaderDirection::Inbound, &source, &request_uri);
+        if rules.is_empty() {
+            return;
+        }
+
+        let mut context = HeaderContext::from_request(request);
+        context.trunk = self.trunk_of(&source).map(str::to_string);
+        match rewrite_headers(request, |headers| apply_rules(headers, &rules, &context)) {
+            Ok(rewritten) => *request = rewritten,
+            Err(e) => debug!("Inbound header rules not applied: {}", e),
+        }
+    }
+
+    /// Drop the headers of `original` the PBX does not know from its
+    /// forwarded copy `request`, unless the outbound rules for `target`
+    /// preserve them
+    pub fn strip_unknown(
+        &self,
+        original: &SipRequest,
+        request: &SipRequest,
+        target: &str,
+    ) -> Result<SipRequest, SipError> {
+        if !self.config.strip_unknown {
+            return Ok(request.clone());
+        }
+        let (_, caller_headers, _) = split_request(original);
+        let rules = self.rules(HeaderDirection::Outbound, uri_host(target), target);
+        let unknown = unknown_headers(&caller_headers, &rules);
+        if unknown.is_empty() {
+            return Ok(request.clone());
+        }
+        debug!("Not forwarding headers {:?} to {}", unknown, target);
+        rewrite_headers(request, |headers| {
+            headers.retain(|(name, _)| !unknown.iter().any(|u| u.eq_ignore_ascii_case(name)))
+        })
+    }
+
+    /// Apply the outbound rules of the trunk and route of `target` to an
+    /// INVITE about to be sent there
+    pub fn apply_outbound(
+        &self,
+        request: &SipRequest,
+        target: &str,
+        context: &HeaderContext,
+    ) -> Result<SipRequest, SipError> {
+        let rules = self.rules(HeaderDirection::Outbound, uri_host(target), target);
+        if rules.is_empty() {
+            return Ok(request.clone());
+        }
+        let mut context = context.clone();
+        context.trunk = self.trunk_of(uri_host(target)).map(str::to_string);
+        rewrite_headers(request, |headers| apply_rules(headers, &rules, &context))
+    }
+
+    /// Headers a request with `headers` ends up with, for checking rules:
+    /// `peer` is the trunk host the request comes from (inbound) or the
+    /// target URI (outbound)
+    pub fn preview(
+        &self,
+        direction: HeaderDirection,
+        peer: &str,
+        request_uri: &str,
+        headers: &[(String, String)],
+        context: &HeaderContext,
+    ) -> Vec<(String, String)> {
+        let host = match direction {
+            HeaderDirection::Inbound => peer,
+            HeaderDirection::Outbound => uri_host(peer),
+        };
+        let rules = self.rules(direction, host, request_uri);
+        let mut context = context.clone();
+        context.trunk = self.trunk_of(host).map(str::to_string);
+
+        let mut result = headers.to_vec();
+        if direction == HeaderDirection::Outbound && self.config.strip_unknown {
+            let unknown = unknown_headers(headers, &rules);
+            result.retain(|(name, _)| !unknown.iter().any(|u| u.eq_ignore_ascii_case(name)));
+        }
+        apply_rules(&mut result, &rules, &context);
+        result
+    }
+}
+
+/// Apply `rules` in order to a request's headers
+fn apply_rules(
+    headers: &mut Vec<(String, String)>,
+    rules: &[&HeaderRule],
+    context: &HeaderContext,
+) {
+    for rule in rules {
+        match rule {
+            HeaderRule::Add { name, value } => headers.push((name.clone(), context.expand(value))),
+            HeaderRule::Remove { name } => {
+                let pattern = name.to_ascii_lowercase();
+                headers.retain(|(header, _)| {
+                    protected_name(header).is_some()
+                        || !wildcard_match(&pattern, &header.to_ascii_lowercase())
+                });
+            }
+            HeaderRule::Preserve { .. } => {}
+        }
+    }
+}
+
+/// Names of the headers in `headers` that are neither known nor preserved
+fn unknown_headers(headers: &[(String, String)], rules: &[&HeaderRule]) -> Vec<String> {
+    let preserved = |name: &str| {
+        rules.iter().any(|rule| match rule {
+            HeaderRule::Preserve { name: pattern } => {
+                wildcard_match(&pattern.to_ascii_lowercase(), &name.to_ascii_lowercase())
+            }
+            _ => false,
+        })
+    };
+    let mut unknown: Vec<String> = Vec::new();
+    for (name, _) in headers {
+        let known = protected_name(name).is_some()
+            || name.len() == 1
+            || KNOWN_HEADERS.iter().any(|h| h.eq_ignore_ascii_case(name));
+        if !known && !preserved(name) && !unknown.iter().any(|u| u.eq_ignore_ascii_case(name)) {
+            unknown.push(name.clone());
+        }
+    }
+    unknown
+}
+
+/// Full name of a protected header, given its name or compact form
+fn protected_name(name: &str) -> Option<&'static str> {
+    PROTECTED_HEADERS
+        .iter()
+        .copied()
+        .find(|header| header.eq_ignore_ascii_case(name))
+        .or_else(|| {
+            COMPACT_FORMS
+                .iter()
+                .find(|(compact, _)| compact.eq_ignore_ascii_case(name))
+                .map(|(_, header)| *header)
+        })
+}
+
+/// Variable names used in a template
+fn template_variables(template: &str) -> Result<Vec<&str>, HeaderRuleError> {
+    let mut variables = Vec::new();
+    let mut rest = template;
+    while let Some(start) = rest.find("${") {
+        let end = rest[start..]
+            .find('}')
+            .ok_or_else(|| HeaderRuleError::UnterminatedVariable(template.to_string()))?;
+        variables.push(&rest[start + 2..start + end]);
+        rest = &rest[start + end + 1..];
+    }
+    Ok(variables)
+}
+
+/// Request line, headers and body of a request
+fn split_request(request: &SipRequest) -> (String, Vec<(String, String)>, String) {
+    let text = String::from_utf8_lossy(&request.to_bytes()).to_string();
+    let (head, body) = text.split_once("\r\n\r\n").unwrap_or((&text, ""));
+    let mut lines = head.split("\r\n");
+    let request_line = lines.next().unwrap_or_default().to_string();
+    let headers = lines
+        .filter_map(|line| line.split_once(':'))
+        .map(|(name, value)| (name.trim().to_string(), value.trim().to_string()))
+        .collect();
+    (request_line, headers, body.to_string())
+}
+
+/// Copy of `request` with its headers edited by `edit`
+fn rewrite_headers(
+    request: &SipRequest,
+    edit: impl FnOnce(&mut Vec<(String, String)>),
+) -> Result<SipRequest, SipError> {
+    let (request_line, mut headers, body) = split_request(request);
+    edit(&mut headers);
+    let headers: Vec<String> = headers
+        .iter()
+        .map(|(name, value)| format!("{}: {}", name, value))
+        .collect();
+    SipRequest::parse(
+        format!(
+            "{}\r\n{}\r\n\r\n{}",
+            request_line,
+            headers.join("\r\n"),
+            body
+        )
+        .as_bytes(),
+    )
+}
+
+/// URI of a name-addr or addr-spec header value
+fn addr_uri(value: &str) -> &str {
+    match (value.find('<'), value.find('>')) {
+        (Some(start), Some(end)) if start < end => &value[start + 1..end],
+        _ => value.split(';').next().unwrap_or(value).trim(),
+    }
+}
+
+/// User part of a SIP URI
+fn uri_user(uri: &str) -> Option<&str> {
+    let rest = uri
+        .strip_prefix("sips:")
+        .or_else(|| uri.strip_prefix("sip:"))
+        .or_else(|| uri.strip_prefix("tel:"))
+        .unwrap_or(uri);
+    let user = match rest.split_once('@') {
+        Some((user, _)) => user,
+        None if uri.starts_with("tel:") => rest,
+        None => return None,
+    };
+    user.split(';').next().filter(|user| !user.is_empty())
+}
+
+#[async_trait]
+impl Explain for HeaderRulesEngine {
+    fn step(&self) -> &'static str {
+        "header_rules"
+    }
+
+    async fn explain(&self, call: &RoutedCall) -> Option<Explanation> {
+        let host = uri_host(&call.target_uri);
+        let trunk = self.trunk_of(host);
+        let route = self.route_for(&call.target_uri);
+        if trunk.is_none() && route.is_none() {
+            return None;
+        }
+
+        let mut matched = Vec::new();
+        if let Some(trunk) = trunk {
+            matched.push(format!("trunk {}", trunk));
+        }
+        if let Some(route) = route {
+            matched.push(format!("route {} ({})", route.name, route.pattern));
+        }
+        let rules: Vec<String> = self
+            .rules(HeaderDirection::Outbound, host, &call.target_uri)
+            .iter()
+            .map(|rule| rule.describe())
+            .collect();
+        let mut rule = matched.join(", ");
+        if !rules.is_empty() {
+            rule = format!("{}: {}", rule, rules.join(", "));
+        }
+
+        let explanation = Explanation::matched(rule);
+        // A trunk chosen by number portability stays
+        match trunk.filter(|_| call.trunk.is_none()) {
+            Some(trunk) => Some(explanation.with_trunk(trunk.to_string())),
+            None => Some(explanation),
+        }
+    }
+}
+
+#[async_trait]
+impl Lint for HeaderRulesEngine {
+    fn source(&self) -> &'static str {
+        "header_rules"
+    }
+
+    /// Routes with an exact or prefix pattern, in order; they pass calls on
+    /// but the first match wins among them
+    async fn inventory(&self, inventory: &mut RoutingInventory) {
+        for route in &self.config.routes {
+            let id = format!("header_route:{}", route.name);
+            let rule = match route.pattern.strip_suffix('*') {
+                Some(prefix) if !prefix.contains('*') => DialRule::prefix(id, prefix),
+                None if !route.pattern.contains('*') => DialRule::exact(id, &route.pattern),
+                _ => continue,
+            };
+            inventory.rule(rule.passing_on());
+        }
+        let mut trunks: Vec<&String> = self.config.trunks.keys().collect();
+        trunks.sort();
+        for trunk in trunks {
+            inventory.reference(&format!("header_trunk:{}", trunk), EntityKind::Trunk, None, trunk);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn invite() -> SipRequest {
+        let text = "INVITE sip:0049301234@carrier.example.com SIP/2.0\r\n\
+                    Via: SIP/2.0/UDP 10.0.0.5:5060;branch=z9hG4bK-1\r\n\
+                    From: \"Alice\" <sip:alice@acme.example.com>;tag=a1\r\n\
+                    To: <sip:0049301234@carrier.example.com>\r\n\
+                    Call-ID: call-1@10.0.0.5\r\n\
+                    CSeq: 1 INVITE\r\n\
+                    Max-Forwards: 70\r\n\
+                    X-Account: 42\r\n\
+                    X-Internal-Debug: on\r\n\
+                    X-Internal-Route: b\r\n\
+                    Content-Length: 0\r\n\r\n";
+        SipRequest::parse(text.as_bytes()).unwrap()
+    }
+
+    fn header_names(request: &SipRequest) -> Vec<String> {
+        split_request(request)
+            .1
+            .into_iter()
+            .map(|(name, _)| name)
+            .collect()
+    }
+
+    fn header<'a>(headers: &'a [(String, String)], name: &str) -> Option<&'a str> {
+        headers
+            .iter()
+            .find(|(n, _)| n.eq_ignore_ascii_case(name))
+            .map(|(_, v)| v.as_str())
+    }
+
+    fn engine(trunk: HeaderRules, route: HeaderRules) -> HeaderRulesEngine {
+        let mut config = HeaderRulesConfig::default();
+        config.trunks.insert(
+            "carrier".to_string(),
+            TrunkHeaderRules {
+                hosts: vec!["carrier.example.com".to_string(), "192.0.2.10".to_string()],
+                rules: trunk,
+            },
+        );
+        config.routes.push(RouteHeaderRules {
+            name: "international".to_string(),
+            pattern: "00*".to_string(),
+            rules: route,
+        });
+        HeaderRulesEngine::new(config).unwrap()
+    }
+
+    #[test]
+    fn test_template_expansion() {
+        let cdr_id = Uuid::new_v4();
+        let context = HeaderContext::from_request(&invite()).with_cdr_id(cdr_id);
+        assert_eq!(context.caller_ext.as_deref(), Some("alice"));
+        assert_eq!(context.tenant.as_deref(), Some("acme.example.com"));
+        assert_eq!(context.callee.as_deref(), Some("0049301234"));
+        // No trunk until the request is forwarded to one
+        assert_eq!(context.expand("via=${trunk};"), "via=;");
+
+        let engine = engine(
+            HeaderRules {
+                outbound: vec![HeaderRule::Add {
+                    name: "X-Billing".to_string(),
+                    value: "${tenant}/${caller_ext};cdr=${cdr_id};via=${trunk}".to_string(),
+                }],
+                ..Default::default()
+            },
+            HeaderRules {
+                outbound: vec![HeaderRule::Add {
+                    name: "X-Route".to_string(),
+                    value: "intl-${callee}".to_string(),
+                }],
+                ..Default::default()
+            },
+        );
+        let target = "sip:0049301234@carrier.example.com";
+        let forwarded = engine.apply_outbound(&invite(), target, &context).unwrap();
+        let (_, headers, _) = split_request(&forwarded);
+        assert_eq!(
+            header(&headers, "X-Billing"),
+            Some(format!("acme.example.com/alice;cdr={};via=carrier", cdr_id).as_str())
+        );
+        assert_eq!(header(&headers, "X-Route"), Some("intl-0049301234"));
+
+        // Rules of other trunks and routes do not apply
+        let local = engine
+            .apply_outbound(&invite(), "sip:200@pbx.example.com", &context)
+            .unwrap();
+        assert!(header(&split_request(&local).1, "X-Billing").is_none());
+    }
+
+    #[test]
+    fn test_removal_patterns_and_preserved_headers() {
+        let engine = engine(
+            HeaderRules {
+                inbound: vec![HeaderRule::Remove {
+                    name: "x-internal-*".to_string(),
+                }],
+                outbound: vec![HeaderRule::Preserve {
+                    name: "X-Account".to_string(),
+                }],
+            },
+            HeaderRules::default(),
+        );
+
+        // Inbound from the trunk: the pattern removes both internal headers
+        let mut request = invite();
+        engine.apply_inbound(&mut request, "192.0.2.10".parse().unwrap());
+        let names = header_names(&request);
+        assert!(names.contains(&"X-Account".to_string()));
+        assert!(!names.iter().any(|n| n.starts_with("X-Internal")));
+        assert!(names.contains(&"Call-ID".to_string()));
+
+        // Requests from elsewhere are untouched
+        let mut request = invite();
+        engine.apply_inbound(&mut request, "198.51.100.1".parse().unwrap());
+        assert!(header_names(&request).contains(&"X-Internal-Debug".to_string()));
+
+        // Forwarded: unknown headers are dropped unless preserved
+        let target = "sip:0049301234@carrier.example.com";
+        let forwarded = engine.strip_unknown(&invite(), &invite(), target).unwrap();
+        let names = header_names(&forwarded);
+        assert!(names.contains(&"X-Account".to_string()));
+        assert!(!names.contains(&"X-Internal-Debug".to_string()));
+        assert!(names.contains(&"Max-Forwards".to_string()));
+    }
+
+    #[test]
+    fn test_protected_headers_rejected() {
+        let add_via = HeaderRule::Add {
+            name: "via".to_string(),
+            value: "SIP/2.0/UDP evil".to_string(),
+        };
+        assert_eq!(
+            add_via.validate(),
+            Err(HeaderRuleError::Protected {
+                rule: "add via".to_string(),
+                header: "Via".to_string(),
+            })
+        );
+        for pattern in ["CSeq", "i", "call-*", "*"] {
+            let rule = HeaderRule::Remove {
+                name: pattern.to_string(),
+            };
+            assert!(
+                matches!(rule.validate(), Err(HeaderRuleError::Protected { .. })),
+                "{} accepted",
+                pattern
+            );
+        }
+        assert!(HeaderRule::Remove {
+            name: "X-*".to_string()
+        }
+        .validate()
+        .is_ok());
+
+        let unknown = HeaderRule::Add {
+            name: "X-Caller".to_string(),
+            value: "${password}".to_string(),
+        };
+        assert_eq!(
+            unknown.validate(),
+            Err(HeaderRuleError::UnknownVariable("password".to_string()))
+        );
+
+        let mut config = HeaderRulesConfig::default();
+        config.routes.push(RouteHeaderRules {
+            name: "all".to_string(),
+            pattern: "*".to_string(),
+            rules: HeaderRules {
+                outbound: vec![HeaderRule::Remove {
+                    name: "Max-*".to_string(),
+                }],
+                ..Default::default()
+            },
+        });
+        assert!(HeaderRulesEngine::new(config).is_err());
+    }
+
+    #[test]
+    fn test_preview() {
+        let engine = engine(
+            HeaderRules {
+                outbound: vec![
+                    HeaderRule::Remove {
+                        name: "X-Account".to_string(),
+                    },
+                    HeaderRule::Add {
+                        name: "X-Account".to_string(),
+                        value: "${tenant}".to_string(),
+                    },
+                ],
+                ..Default::default()
+            },
+            HeaderRules::default(),
+        );
+        let headers = vec![
+            ("X-Account".to_string(), "42".to_string()),
+            ("X-Other".to_string(), "1".to_string()),
+            ("Supported".to_string(), "timer".to_string()),
+        ];
+        let context = HeaderContext {
+            tenant: Some("acme".to_string()),
+            ..Default::default()
+        };
+        let result = engine.preview(
+            HeaderDirection::Outbound,
+            "sip:0049301234@carrier.example.com",
+            "sip:0049301234@carrier.example.com",
+            &headers,
+            &context,
+        );
+        assert_eq!(
+            result,
+            vec![
+                ("Supported".to_string(), "timer".to_string()),
+                ("X-Account".to_string(), "acme".to_string()),
+            ]
+        );
+    }
+}
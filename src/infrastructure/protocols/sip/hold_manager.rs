@@ -1,6 +1,7 @@
 /// Call hold/resume manager using re-INVITE
 use std::collections::HashMap;
 use std::sync::Arc;
+use std::time::Instant;
 use tokio::sync::RwLock;
 use tracing::{debug, info};
 
@@ -24,6 +25,8 @@ pub struct HoldInfo {
     pub state: HoldState,
     pub local_sdp: Option<String>,
     pub remote_sdp: Option<String>,
+    /// When the local party put the call on hold, while it holds
+    pub held_since: Option<Instant>,
 }
 
 /// Manages call hold/resume state
@@ -46,11 +49,13 @@ impl HoldManager {
             match info.state {
                 HoldState::Active => {
                     info.state = HoldState::LocalHold;
+                    info.held_since = Some(Instant::now());
                     info!("Call {} placed on hold", call_id);
                     Ok(())
                 }
                 HoldState::RemoteHold => {
                     info.state = HoldState::BothHold;
+                    info.held_since = Some(Instant::now());
                     info!("Call {} placed on hold (both parties)", call_id);
                     Ok(())
                 }
@@ -65,6 +70,7 @@ impl HoldManager {
                     state: HoldState::LocalHold,
                     local_sdp: None,
                     remote_sdp: None,
+                    held_since: Some(Instant::now()),
                 },
             );
             info!("Call {} placed on hold (new)", call_id);
@@ -80,11 +86,13 @@ impl HoldManager {
             match info.state {
                 HoldState::LocalHold => {
                     info.state = HoldState::Active;
+                    info.held_since = None;
                     info!("Call {} resumed from hold", call_id);
                     Ok(())
                 }
                 HoldState::BothHold => {
                     info.state = HoldState::RemoteHold;
+                    info.held_since = None;
                     info!("Call {} resumed from hold (remote still holding)", call_id);
                     Ok(())
                 }
@@ -122,6 +130,7 @@ impl HoldManager {
                     state: HoldState::RemoteHold,
                     local_sdp: None,
                     remote_sdp: None,
+                    held_since: None,
                 },
             );
             debug!("Call {} - remote party placed on hold (new)", call_id);
@@ -158,6 +167,15 @@ impl HoldManager {
         holds.get(call_id).map(|info| info.state.clone())
     }
 
+    /// Calls the local party holds, with when their hold began
+    pub async fn local_holds(&self) -> Vec<(String, Instant)> {
+        let holds = self.holds.read().await;
+        holds
+            .values()
+            .filter_map(|info| info.held_since.map(|since| (info.call_id.clone(), since)))
+            .collect()
+    }
+
     /// Update SDP for a call
     pub async fn update_sdp(&self, call_id: &str, local_sdp: Option<String>, remote_sdp: Option<String>) {
         let mut holds = self.holds.write().await;
@@ -362,6 +380,24 @@ mod tests {
         assert_eq!(SdpHoldHelper::detect_hold_state(inactive_sdp), HoldState::BothHold);
     }
 
+    #[tokio::test]
+    async fn test_local_holds() {
+        let manager = HoldManager::new();
+
+        manager.remote_hold("remote-only").await.unwrap();
+        manager.hold_call("held").await.unwrap();
+        let holds = manager.local_holds().await;
+        assert_eq!(holds.len(), 1);
+        assert_eq!(holds[0].0, "held");
+
+        // Still held locally while the remote party holds too
+        manager.remote_hold("held").await.unwrap();
+        assert_eq!(manager.local_holds().await.len(), 1);
+
+        manager.resume_call("held").await.unwrap();
+        assert!(manager.local_holds().await.is_empty());
+    }
+
     #[tokio::test]
     async fn test_is_on_hold() {
         let manager = HoldManager::new();
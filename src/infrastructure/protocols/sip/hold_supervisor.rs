@@ -0,0 +1,654 @@
+//! Hold supervision
+//!
+//! A caller put on hold and forgotten waits on music until they give up.
+//! [`HoldSupervisor`] watches calls on local hold: once per reminder
+//! interval the holder hears a short prompt and a reminder event is
+//! published; when the maximum hold time is reached the call is recovered
+//! by re-ringing the holder, transferring the held party to a fallback
+//! (operator, queue or voicemail), or ending the call after a goodbye
+//! prompt. Re-ring and transfer reuse the blind transfer path, so a holder
+//! who does not answer still leaves the call recovered or cleanly ended.
+//!
+//! Parked calls and calls waiting for a blind transfer have their own
+//! timers and are left alone.
+
+use super::call_router::{CallRouter, HeldCall};
+use super::message::SipError;
+use super::redirect::InviteForwarder;
+use super::transfer::{ReferNotify, TransferNotifier, TransferOutcome};
+use crate::domain::call_announcer::{AnnouncementRequest, AnnouncementType, CallAnnouncer};
+use crate::domain::call_parking::CallParkingManager;
+use crate::domain::tenant_branding::BrandingRegistry;
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::sync::broadcast;
+use tokio::task::JoinHandle;
+use tracing::{debug, info, warn};
+
+/// What happens to a call held past its maximum hold time
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum HoldRecovery {
+    /// Ring the holder again; the call resumes when they answer
+    Rering,
+    /// Transfer the held party to the fallback URI
+    Transfer,
+    /// Play the goodbye prompt to the held party and end the call
+    Terminate,
+}
+
+/// Hold reminders and maximum hold time
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct HoldPolicy {
+    pub enabled: bool,
+    /// Seconds between reminders to the holder (0 = no reminders)
+    pub reminder_interval_secs: u64,
+    /// Seconds a call may stay on hold before it is recovered (0 = no limit)
+    pub max_hold_secs: u64,
+    pub recovery: HoldRecovery,
+    /// Destination of [`HoldRecovery::Transfer`]
+    pub fallback_uri: Option<String>,
+    /// Audio file played to the holder on each reminder
+    pub reminder_prompt: String,
+    /// Audio file played to the held party before the call is ended
+    pub goodbye_prompt: String,
+    /// Seconds the goodbye prompt gets before the call is ended
+    pub goodbye_delay_secs: u64,
+    /// Per-tenant overrides, keyed by realm
+    pub tenants: HashMap<String, HoldTimers>,
+    /// Per-user overrides, keyed by `user@realm`
+    pub users: HashMap<String, HoldTimers>,
+}
+
+impl Default for HoldPolicy {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            reminder_interval_secs: 60,
+            max_hold_secs: 900,
+            recovery: HoldRecovery::Rering,
+            fallback_uri: None,
+            reminder_prompt: "hold_reminder".to_string(),
+            goodbye_prompt: "hold_goodbye".to_string(),
+            goodbye_delay_secs: 5,
+            tenants: HashMap::new(),
+            users: HashMap::new(),
+        }
+    }
+}
+
+/// Overrides of the global hold timers; unset fields inherit
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct HoldTimers {
+    pub reminder_interval_secs: Option<u64>,
+    pub max_hold_secs: Option<u64>,
+    pub recovery: Option<HoldRecovery>,
+    pub fallback_uri: Option<String>,
+}
+
+/// Hold timers in effect for one holder
+#[derive(Debug, Clone, PartialEq)]
+pub struct HoldLimits {
+    pub reminder_interval: Option<Duration>,
+    pub max_hold: Option<Duration>,
+    pub recovery: HoldRecovery,
+    pub fallback_uri: Option<String>,
+}
+
+impl HoldPolicy {
+    /// Timers for calls held by `holder_uri`: the user's override, else
+    /// the tenant's, else the global setting
+    pub fn limits_for(&self, holder_uri: &str) -> HoldLimits {
+        let (user, realm) = user_and_realm(holder_uri);
+        let user = user.and_then(|user| self.users.get(&format!("{}@{}", user, realm)));
+        let tenant = self.tenants.get(realm);
+        let pick = |field: fn(&HoldTimers) -> Option<u64>, global: u64| {
+            user.and_then(field)
+                .or_else(|| tenant.and_then(field))
+                .unwrap_or(global)
+        };
+        let secs = |secs: u64| (secs > 0).then(|| Duration::from_secs(secs));
+
+        HoldLimits {
+            reminder_interval: secs(pick(
+                |t| t.reminder_interval_secs,
+                self.reminder_interval_secs,
+            )),
+            max_hold: secs(pick(|t| t.max_hold_secs, self.max_hold_secs)),
+            recovery: user
+                .and_then(|t| t.recovery)
+                .or_else(|| tenant.and_then(|t| t.recovery))
+                .unwrap_or(self.recovery),
+            fallback_uri: user
+                .and_then(|t| t.fallback_uri.clone())
+                .or_else(|| tenant.and_then(|t| t.fallback_uri.clone()))
+                .or_else(|| self.fallback_uri.clone()),
+        }
+    }
+}
+
+/// User and host of a SIP URI (`sip:alice@example.com;transport=tcp`)
+fn user_and_realm(uri: &str) -> (Option<&str>, &str) {
+    let uri = uri.trim_start_matches('<');
+    let rest = uri
+        .strip_prefix("sips:")
+        .or_else(|| uri.strip_prefix("sip:"))
+        .unwrap_or(uri);
+    let rest = rest.split([';', '>', '?']).next().unwrap_or(rest);
+    match rest.split_once('@') {
+        Some((user, host)) => (Some(user), host.split(':').next().unwrap_or(host)),
+        None => (None, rest.split(':').next().unwrap_or(rest)),
+    }
+}
+
+/// Published while supervising held calls
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum HoldSupervisionEvent {
+    /// The holder was reminded of a waiting party
+    Reminder {
+        call_id: String,
+        holder_uri: String,
+        held_secs: u64,
+        /// 1 for the first reminder of this hold
+        reminder: u32,
+    },
+    /// The maximum hold time was reached and the call is being recovered
+    Recovery {
+        call_id: String,
+        holder_uri: String,
+        held_uri: String,
+        held_secs: u64,
+        action: HoldRecovery,
+    },
+}
+
+/// Transfers started by the PBX itself (hold recovery, announcement
+/// follow-ups) have no REFER to report to
+pub(super) struct NoTransferor;
+
+#[async_trait]
+impl TransferNotifier for NoTransferor {
+    async fn notify(
+        &self,
+        _call_id: &str,
+        _transferor_uri: &str,
+        _notify: &ReferNotify,
+    ) -> Result<(), SipError> {
+        Ok(())
+    }
+}
+
+/// Reminds holders of waiting calls and recovers calls held too long
+pub struct HoldSupervisor {
+    router: Arc<CallRouter>,
+    policy: HoldPolicy,
+    announcer: Option<Arc<CallAnnouncer>>,
+    parking: Option<Arc<CallParkingManager>>,
+    forwarder: Option<Arc<dyn InviteForwarder>>,
+    /// Plays prompts in the tenant's language and prompt set
+    branding: Option<Arc<BrandingRegistry>>,
+    /// Start of the hold and reminders sent for it, per call
+    reminded: Mutex<HashMap<String, (Instant, u32)>>,
+    /// Calls whose recovery is running
+    recovering: Mutex<HashSet<String>>,
+    events: broadcast::Sender<HoldSupervisionEvent>,
+}
+
+impl HoldSupervisor {
+    pub fn new(router: Arc<CallRouter>, policy: HoldPolicy) -> Self {
+        let (events, _) = broadcast::channel(256);
+        Self {
+            router,
+            policy,
+            announcer: None,
+            parking: None,
+            forwarder: None,
+            branding: None,
+            reminded: Mutex::new(HashMap::new()),
+            recovering: Mutex::new(HashSet::new()),
+            events,
+        }
+    }
+
+    /// Play the reminder and goodbye prompts
+    pub fn with_announcer(mut self, announcer: Arc<CallAnnouncer>) -> Self {
+        self.announcer = Some(announcer);
+        self
+    }
+
+    /// Leave parked calls to the parking timeout
+    pub fn with_parking(mut self, parking: Arc<CallParkingManager>) -> Self {
+        self.parking = Some(parking);
+        self
+    }
+
+    /// Send the INVITEs of re-ring and transfer recoveries; without one,
+    /// calls held too long are ended
+    pub fn with_forwarder(mut self, forwarder: Arc<dyn InviteForwarder>) -> Self {
+        self.forwarder = Some(forwarder);
+        self
+    }
+
+    /// Play prompts in the language and prompt set of the call's tenant
+    pub fn with_branding(mut self, branding: Arc<BrandingRegistry>) -> Self {
+        self.branding = Some(branding);
+        self
+    }
+
+    /// `request` in the language and prompt set of the call's tenant
+    fn branded(&self, call: &HeldCall, request: AnnouncementRequest) -> AnnouncementRequest {
+        match &self.branding {
+            Some(branding) => {
+                let tenant = branding.tenant_of(&call.holder_uri, &call.held_uri);
+                branding.brand_announcement(tenant.as_deref(), request)
+            }
+            None => request,
+        }
+    }
+
+    /// Reminder and recovery events
+    pub fn subscribe(&self) -> broadcast::Receiver<HoldSupervisionEvent> {
+        self.events.subscribe()
+    }
+
+    /// Check held calls every `tick`
+    pub fn spawn(self: Arc<Self>, tick: Duration) -> JoinHandle<()> {
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(tick);
+            loop {
+                interval.tick().await;
+                self.check_at(Instant::now()).await;
+            }
+        })
+    }
+
+    /// Send due reminders and start due recoveries as of `now`
+    pub async fn check_at(self: &Arc<Self>, now: Instant) {
+        let held = self.router.held_calls().await;
+        {
+            let mut reminded = self.reminded.lock().unwrap();
+            reminded.retain(|call_id, _| held.iter().any(|call| &call.call_id == call_id));
+        }
+
+        for call in held {
+            if self.is_parked(&call.call_id) {
+                continue;
+            }
+            let held_for = now.saturating_duration_since(call.held_since);
+            let limits = self.policy.limits_for(&call.holder_uri);
+
+            if limits.max_hold.is_some_and(|max| held_for >= max) {
+                self.start_recovery(call, held_for, &limits);
+                continue;
+            }
+            if let Some(interval) = limits.reminder_interval {
+                self.remind_if_due(&call, held_for, interval);
+            }
+        }
+    }
+
+    fn is_parked(&self, call_id: &str) -> bool {
+        self.parking
+            .as_ref()
+            .is_some_and(|parking| parking.find_slot_by_call_id(call_id).is_some())
+    }
+
+    fn remind_if_due(&self, call: &HeldCall, held_for: Duration, interval: Duration) {
+        let due = (held_for.as_secs() / interval.as_secs().max(1)) as u32;
+        {
+            let mut reminded = self.reminded.lock().unwrap();
+            let entry = reminded
+                .entry(call.call_id.clone())
+                .or_insert((call.held_since, 0));
+            if entry.0 != call.held_since {
+                // Resumed and held again since the last check
+                *entry = (call.held_since, 0);
+            }
+            if due <= entry.1 {
+                return;
+            }
+            entry.1 = due;
+        }
+
+        debug!(
+            "Reminding {} of call {} on hold for {}s",
+            call.holder_uri,
+            call.call_id,
+            held_for.as_secs()
+        );
+        if let Some(announcer) = &self.announcer {
+            let request = AnnouncementRequest::new(call.call_id.clone(), AnnouncementType::Custom)
+                .to_party(&call.holder_uri)
+                .add_audio(&self.policy.reminder_prompt);
+            let request = self.branded(call, request);
+            if let Err(e) = announcer.play_announcement(request) {
+                warn!(
+                    "Failed to play hold reminder on call {}: {}",
+                    call.call_id, e
+                );
+            }
+        }
+        let _ = self.events.send(HoldSupervisionEvent::Reminder {
+            call_id: call.call_id.clone(),
+            holder_uri: call.holder_uri.clone(),
+            held_secs: held_for.as_secs(),
+            reminder: due,
+        });
+    }
+
+    fn start_recovery(self: &Arc<Self>, call: HeldCall, held_for: Duration, limits: &HoldLimits) {
+        if !self.recovering.lock().unwrap().insert(call.call_id.clone()) {
+            return;
+        }
+        info!(
+            "Call {} held by {} for {}s, recovering ({:?})",
+            call.call_id,
+            call.holder_uri,
+            held_for.as_secs(),
+            limits.recovery
+        );
+        let _ = self.events.send(HoldSupervisionEvent::Recovery {
+            call_id: call.call_id.clone(),
+            holder_uri: call.holder_uri.clone(),
+            held_uri: call.held_uri.clone(),
+            held_secs: held_for.as_secs(),
+            action: limits.recovery,
+        });
+
+        let supervisor = self.clone();
+        let limits = limits.clone();
+        tokio::spawn(async move {
+            supervisor.recover(&call, &limits).await;
+            supervisor.recovering.lock().unwrap().remove(&call.call_id);
+        });
+    }
+
+    async fn recover(&self, call: &HeldCall, limits: &HoldLimits) {
+        let target = match (limits.recovery, &limits.fallback_uri) {
+            (HoldRecovery::Terminate, _) => None,
+            (HoldRecovery::Rering, _) => Some(call.holder_uri.as_str()),
+            (HoldRecovery::Transfer, Some(fallback)) => Some(fallback.as_str()),
+            (HoldRecovery::Transfer, None) => {
+                warn!(
+                    "No fallback for held call {}, ringing {} again",
+                    call.call_id, call.holder_uri
+                );
+                Some(call.holder_uri.as_str())
+            }
+        };
+
+        match (target, &self.forwarder) {
+            (Some(target), Some(forwarder)) => {
+                match self.redirect(call, target, forwarder.as_ref()).await {
+                    Ok(outcome) => {
+                        info!("Hold recovery of call {}: {:?}", call.call_id, outcome);
+                        return;
+                    }
+                    Err(e) => warn!("Hold recovery of call {} failed: {}", call.call_id, e),
+                }
+            }
+            (Some(_), None) => warn!(
+                "No forwarder for hold recovery of call {}, ending it",
+                call.call_id
+            ),
+            (None, _) => {}
+        }
+        self.terminate(call).await;
+    }
+
+    /// Connect the held party to `target` in place of the holder
+    async fn redirect(
+        &self,
+        call: &HeldCall,
+        target: &str,
+        forwarder: &dyn InviteForwarder,
+    ) -> Result<TransferOutcome, String> {
+        self.router
+            .blind_transfer_from(&call.call_id, &call.holder_uri, target)
+            .await?;
+        self.router
+            .execute_transfer(&call.call_id, forwarder, &NoTransferor)
+            .await
+    }
+
+    async fn terminate(&self, call: &HeldCall) {
+        if let Some(announcer) = &self.announcer {
+            let request = AnnouncementRequest::new(call.call_id.clone(), AnnouncementType::Goodbye)
+                .to_party(&call.held_uri)
+                .add_audio(&self.policy.goodbye_prompt);
+            let request = self.branded(call, request);
+            match announcer.play_announcement(request) {
+                Ok(_) => {
+                    tokio::time::sleep(Duration::from_secs(self.policy.goodbye_delay_secs)).await
+                }
+                Err(e) => warn!("Failed to play goodbye on call {}: {}", call.call_id, e),
+            }
+        }
+        match self.router.terminate_call(&call.call_id).await {
+            Ok(()) => info!("Ended call {} held too long", call.call_id),
+            Err(e) => debug!("Held call {} already gone: {}", call.call_id, e),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::domain::call_parking::ParkingLotConfig;
+    use crate::infrastructure::protocols::sip::builder::ResponseBuilder;
+    use crate::infrastructure::protocols::sip::message::{SipRequest, SipResponse};
+    use crate::infrastructure::protocols::sip::registrar::Registrar;
+
+    /// Answers every INVITE with `status`, recording the targets
+    struct Answering {
+        status: u16,
+        invited: Mutex<Vec<String>>,
+    }
+
+    #[async_trait]
+    impl InviteForwarder for Answering {
+        async fn forward(
+            &self,
+            target: &str,
+            request: &SipRequest,
+        ) -> Result<SipResponse, SipError> {
+            self.invited.lock().unwrap().push(target.to_string());
+            ResponseBuilder::new(self.status).build_for_request(request)
+        }
+    }
+
+    fn policy(recovery: HoldRecovery) -> HoldPolicy {
+        HoldPolicy {
+            enabled: true,
+            reminder_interval_secs: 10,
+            max_hold_secs: 30,
+            recovery,
+            goodbye_delay_secs: 0,
+            ..Default::default()
+        }
+    }
+
+    /// Alice calling Bob, who puts her on hold
+    async fn held_call(router: &CallRouter) -> Instant {
+        router
+            .create_call(
+                "call-held".to_string(),
+                "sip:alice@example.com".to_string(),
+                "sip:bob@example.com".to_string(),
+            )
+            .await
+            .unwrap();
+        router.answer_call("call-held").await.unwrap();
+        router
+            .hold_call_from("call-held", "sip:bob@example.com")
+            .await
+            .unwrap();
+        router.held_calls().await[0].held_since
+    }
+
+    fn drain(events: &mut broadcast::Receiver<HoldSupervisionEvent>) -> Vec<HoldSupervisionEvent> {
+        std::iter::from_fn(|| events.try_recv().ok()).collect()
+    }
+
+    #[test]
+    fn test_limits_precedence() {
+        let mut policy = policy(HoldRecovery::Rering);
+        policy.tenants.insert(
+            "example.com".to_string(),
+            HoldTimers {
+                max_hold_secs: Some(120),
+                recovery: Some(HoldRecovery::Transfer),
+                fallback_uri: Some("sip:operator@example.com".to_string()),
+                ..Default::default()
+            },
+        );
+        policy.users.insert(
+            "bob@example.com".to_string(),
+            HoldTimers {
+                reminder_interval_secs: Some(0),
+                max_hold_secs: Some(600),
+                ..Default::default()
+            },
+        );
+
+        let bob = policy.limits_for("<sip:bob@example.com;transport=tcp>");
+        assert_eq!(bob.reminder_interval, None);
+        assert_eq!(bob.max_hold, Some(Duration::from_secs(600)));
+        assert_eq!(bob.recovery, HoldRecovery::Transfer);
+        assert_eq!(
+            bob.fallback_uri.as_deref(),
+            Some("sip:operator@example.com")
+        );
+
+        let carol = policy.limits_for("sip:carol@example.com");
+        assert_eq!(carol.reminder_interval, Some(Duration::from_secs(10)));
+        assert_eq!(carol.max_hold, Some(Duration::from_secs(120)));
+
+        let dave = policy.limits_for("sip:dave@other.example.org");
+        assert_eq!(dave.max_hold, Some(Duration::from_secs(30)));
+        assert_eq!(dave.recovery, HoldRecovery::Rering);
+        assert_eq!(dave.fallback_uri, None);
+    }
+
+    #[tokio::test]
+    async fn test_reminder_once_per_interval() {
+        let router = Arc::new(CallRouter::new(Arc::new(Registrar::new())));
+        let since = held_call(&router).await;
+        let supervisor = Arc::new(HoldSupervisor::new(
+            router.clone(),
+            policy(HoldRecovery::Terminate),
+        ));
+        let mut events = supervisor.subscribe();
+
+        supervisor.check_at(since + Duration::from_secs(5)).await;
+        assert!(drain(&mut events).is_empty());
+
+        supervisor.check_at(since + Duration::from_secs(11)).await;
+        supervisor.check_at(since + Duration::from_secs(15)).await;
+        supervisor.check_at(since + Duration::from_secs(21)).await;
+        let reminders = drain(&mut events);
+        assert_eq!(reminders.len(), 2);
+        assert!(matches!(
+            &reminders[1],
+            HoldSupervisionEvent::Reminder { holder_uri, reminder: 2, held_secs: 21, .. }
+                if holder_uri == "sip:bob@example.com"
+        ));
+        assert!(router.is_call_on_hold("call-held").await);
+    }
+
+    #[tokio::test]
+    async fn test_terminate_at_max_hold() {
+        let router = Arc::new(CallRouter::new(Arc::new(Registrar::new())));
+        let since = held_call(&router).await;
+        let supervisor = Arc::new(HoldSupervisor::new(
+            router.clone(),
+            policy(HoldRecovery::Terminate),
+        ));
+        let mut events = supervisor.subscribe();
+
+        supervisor.check_at(since + Duration::from_secs(31)).await;
+        supervisor.check_at(since + Duration::from_secs(32)).await;
+        for _ in 0..50 {
+            if router.active_call_count().await == 0 {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+
+        assert_eq!(router.active_call_count().await, 0);
+        let events = drain(&mut events);
+        assert_eq!(events.len(), 1);
+        assert!(matches!(
+            &events[0],
+            HoldSupervisionEvent::Recovery { held_uri, action: HoldRecovery::Terminate, .. }
+                if held_uri == "sip:alice@example.com"
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_transfer_to_fallback() {
+        let router = Arc::new(CallRouter::new(Arc::new(Registrar::new())));
+        let since = held_call(&router).await;
+        let mut policy = policy(HoldRecovery::Transfer);
+        policy.fallback_uri = Some("sip:operator@example.com".to_string());
+        let forwarder = Arc::new(Answering {
+            status: 200,
+            invited: Mutex::new(Vec::new()),
+        });
+        let supervisor =
+            Arc::new(HoldSupervisor::new(router.clone(), policy).with_forwarder(forwarder.clone()));
+
+        supervisor.check_at(since + Duration::from_secs(30)).await;
+        for _ in 0..50 {
+            if !router.is_call_on_hold("call-held").await {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+
+        assert_eq!(
+            forwarder.invited.lock().unwrap().as_slice(),
+            ["sip:operator@example.com"]
+        );
+        let call = router.get_active_call("call-held").await.unwrap();
+        assert!(!call.on_hold);
+        assert_eq!(call.caller_uri, "sip:alice@example.com");
+        assert_eq!(call.callee_uri, "sip:operator@example.com");
+    }
+
+    #[tokio::test]
+    async fn test_parked_calls_are_left_alone() {
+        let router = Arc::new(CallRouter::new(Arc::new(Registrar::new())));
+        let since = held_call(&router).await;
+        let parking = Arc::new(CallParkingManager::new());
+        parking
+            .create_lot(ParkingLotConfig::new("Lot".to_string(), 700, 710))
+            .unwrap();
+        parking
+            .park_call(
+                "call-held".to_string(),
+                "sip:bob@example.com".to_string(),
+                "sip:alice@example.com".to_string(),
+                "sip:bob@example.com".to_string(),
+                None,
+            )
+            .unwrap();
+        let supervisor = Arc::new(
+            HoldSupervisor::new(router.clone(), policy(HoldRecovery::Terminate))
+                .with_parking(parking),
+        );
+        let mut events = supervisor.subscribe();
+
+        supervisor.check_at(since + Duration::from_secs(60)).await;
+        tokio::time::sleep(Duration::from_millis(20)).await;
+
+        assert!(drain(&mut events).is_empty());
+        assert_eq!(router.active_call_count().await, 1);
+    }
+}
@@ -0,0 +1,340 @@
+//! Max-Forwards and loop detection for forwarded requests
+//!
+//! Requests we forward get Max-Forwards decremented (70 when missing) and
+//! are refused with 483 Too Many Hops once it reaches zero, so a forwarding
+//! rule bouncing calls between two servers cannot loop forever.
+//!
+//! As a stateful B2BUA we put our own Via with a fresh branch on every
+//! forwarded request (RFC 3261 16.6). The branch is issued once per received
+//! request and target, so retransmissions (and the CANCEL or ACK of a
+//! forwarded INVITE) reuse it. A request arriving with one of our Vias and a
+//! branch we issued has looped back and is answered with 482 Loop Detected.
+
+use super::message::{SipError, SipMethod, SipRequest};
+use super::redirect::retarget;
+use super::transaction::TransactionId;
+use rsip::headers::UntypedHeader;
+use rsip::Header;
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use tracing::warn;
+
+pub use super::quirks::DEFAULT_MAX_FORWARDS;
+
+/// How long issued branches are remembered (INVITE transactions can last
+/// until Timer C, 3 minutes)
+const BRANCH_TTL: Duration = Duration::from_secs(300);
+
+/// Max-Forwards of a request, if present and valid
+pub fn max_forwards(request: &SipRequest) -> Option<u32> {
+    request.headers().iter().find_map(|h| match h {
+        Header::MaxForwards(value) => value.value().trim().parse().ok(),
+        _ => None,
+    })
+}
+
+/// Max-Forwards for a request forwarded from `request`, or None when its
+/// hops are used up and it must be answered with 483
+pub fn forwarded_max_forwards(request: &SipRequest) -> Option<u32> {
+    match max_forwards(request).unwrap_or(DEFAULT_MAX_FORWARDS) {
+        0 => None,
+        hops => Some(hops - 1),
+    }
+}
+
+/// Branch and sent-by of every Via of a request, topmost first
+fn vias(request: &SipRequest) -> Vec<(String, Option<String>)> {
+    request
+        .headers()
+        .iter()
+        .filter_map(|h| match h {
+            Header::Via(via) => Some(via.value().to_string()),
+            _ => None,
+        })
+        .flat_map(|value| {
+            value
+                .split(',')
+                .map(|via| {
+                    let mut params = via.split(';');
+                    let sent_by = params
+                        .next()
+                        .and_then(|protocol| protocol.split_whitespace().nth(1))
+                        .unwrap_or_default()
+                        .to_lowercase();
+                    let branch = params
+                        .find_map(|p| p.trim().strip_prefix("branch="))
+                        .map(|b| b.trim().to_string());
+                    (sent_by, branch)
+                })
+                .collect::<Vec<_>>()
+        })
+        .collect()
+}
+
+/// Branches issued for forwarded requests
+struct Branches {
+    /// Received request (top branch) and target -> branch issued for it
+    by_request: HashMap<String, String>,
+    /// Issued branch -> when
+    issued: HashMap<String, Instant>,
+}
+
+/// Issues Via branches for forwarded requests and detects requests that
+/// looped back
+pub struct HopTracker {
+    /// Our Via sent-by (host:port)
+    sent_by: String,
+    branches: Mutex<Branches>,
+}
+
+impl HopTracker {
+    pub fn new(sent_by: impl Into<String>) -> Self {
+        Self {
+            sent_by: sent_by.into(),
+            branches: Mutex::new(Branches {
+                by_request: HashMap::new(),
+                issued: HashMap::new(),
+            }),
+        }
+    }
+
+    /// Status to answer a received request with instead of handling it:
+    /// 482 when it carries a Via of ours with a branch we issued
+    ///
+    /// ACKs are never answered and pass.
+    pub fn check(&self, request: &SipRequest) -> Option<u16> {
+        if request.method() == Some(SipMethod::Ack) {
+            return None;
+        }
+        let branches = self.branches.lock().unwrap();
+        let looped = vias(request).into_iter().any(|(sent_by, branch)| {
+            sent_by.eq_ignore_ascii_case(&self.sent_by)
+                && branch.is_some_and(|b| branches.issued.contains_key(&b))
+        });
+        if looped {
+            warn!(
+                "Request {} looped back to us",
+                request.call_id().unwrap_or_default()
+            );
+            return Some(482);
+        }
+        None
+    }
+
+    /// Branch for forwarding `received` to `target`
+    ///
+    /// Retransmissions of `received` get the same branch, and so do its
+    /// CANCEL and non-2xx ACK, which share the received branch.
+    pub fn branch_for(&self, received: &SipRequest, target: &str) -> String {
+        let received_branch = vias(received)
+            .into_iter()
+            .next()
+            .and_then(|(_, branch)| branch)
+            .unwrap_or_else(|| {
+                // RFC 2543 peers: identify the request by Call-ID and CSeq
+                format!(
+                    "{}-{}",
+                    received.call_id().unwrap_or_default(),
+                    received.cseq().unwrap_or_default()
+                )
+            });
+        let key = format!("{}|{}", received_branch, target.to_lowercase());
+
+        let mut branches = self.branches.lock().unwrap();
+        let now = Instant::now();
+        branches
+            .issued
+            .retain(|_, at| now.duration_since(*at) < BRANCH_TTL);
+        let Branches {
+            by_request, issued, ..
+        } = &mut *branches;
+        by_request.retain(|_, branch| issued.contains_key(branch));
+
+        if let Some(branch) = by_request.get(&key) {
+            return branch.clone();
+        }
+        let branch = TransactionId::generate().0;
+        issued.insert(branch.clone(), now);
+        by_request.insert(key, branch.clone());
+        branch
+    }
+
+    /// `received` forwarded to `target`: retargeted, Max-Forwards
+    /// decremented and our Via on top
+    ///
+    /// Check `forwarded_max_forwards` first; used-up hops are answered
+    /// with 483.
+    pub fn forward(&self, received: &SipRequest, target: &str) -> Result<SipRequest, SipError> {
+        let forwarded = retarget(received, target)?;
+        self.push_via(&forwarded, &self.branch_for(received, target), target)
+    }
+
+    fn push_via(
+        &self,
+        request: &SipRequest,
+        branch: &str,
+        target: &str,
+    ) -> Result<SipRequest, SipError> {
+        let transport = if target.to_lowercase().contains("transport=tcp") {
+            "TCP"
+        } else {
+            "UDP"
+        };
+        let text = String::from_utf8_lossy(&request.to_bytes()).to_string();
+        let (request_line, rest) = text
+            .split_once("\r\n")
+            .ok_or_else(|| SipError::ParseError("Request has no request line".to_string()))?;
+        let via = format!(
+            "Via: SIP/2.0/{} {};branch={}",
+            transport, self.sent_by, branch
+        );
+        SipRequest::parse(format!("{}\r\n{}\r\n{}", request_line, via, rest).as_bytes())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::infrastructure::protocols::sip::builder::ResponseBuilder;
+    use crate::infrastructure::protocols::sip::message::SipResponse;
+    use crate::infrastructure::protocols::sip::redirect::{
+        InviteForwarder, RedirectFollower, RedirectPolicy, TrustZone,
+    };
+    use async_trait::async_trait;
+    use std::sync::atomic::{AtomicU32, Ordering};
+    use std::sync::{Arc, OnceLock};
+
+    /// Server whose forwarding rule sends every INVITE to its peer
+    struct LoopingServer {
+        hops: Arc<HopTracker>,
+        peer: OnceLock<Arc<LoopingServer>>,
+        /// Drop the Vias of received requests, as a B2BUA re-originating
+        /// them would
+        b2bua: bool,
+        received: AtomicU32,
+    }
+
+    impl LoopingServer {
+        fn new(sent_by: &str, b2bua: bool) -> Arc<Self> {
+            Arc::new(Self {
+                hops: Arc::new(HopTracker::new(sent_by)),
+                peer: OnceLock::new(),
+                b2bua,
+                received: AtomicU32::new(0),
+            })
+        }
+    }
+
+    #[async_trait]
+    impl InviteForwarder for LoopingServer {
+        async fn forward(
+            &self,
+            target: &str,
+            request: &SipRequest,
+        ) -> Result<SipResponse, SipError> {
+            self.received.fetch_add(1, Ordering::SeqCst);
+            let request = if self.b2bua {
+                let text = String::from_utf8_lossy(&request.to_bytes()).to_string();
+                let text: Vec<&str> = text
+                    .split("\r\n")
+                    .filter(|line| !line.starts_with("Via:"))
+                    .collect();
+                SipRequest::parse(text.join("\r\n").as_bytes())?
+            } else {
+                request.clone()
+            };
+
+            if let Some(status) = self.hops.check(&request) {
+                return ResponseBuilder::new(status).build_for_request(&request);
+            }
+            let peer = self.peer.get().unwrap().clone();
+            RedirectFollower::new(RedirectPolicy::default())
+                .with_hop_tracker(self.hops.clone())
+                .forward(peer.as_ref(), TrustZone::External, target, &request)
+                .await
+                .map(|outcome| outcome.response)
+        }
+    }
+
+    fn invite(max_forwards: Option<u32>) -> SipRequest {
+        let max_forwards = max_forwards
+            .map(|hops| format!("Max-Forwards: {}\r\n", hops))
+            .unwrap_or_default();
+        let text = format!(
+            "INVITE sip:1001@b.example.net SIP/2.0\r\n\
+             Via: SIP/2.0/UDP 10.0.0.9:5060;branch=z9hG4bKcaller\r\n\
+             {}\
+             From: <sip:alice@a.example.net>;tag=a1\r\n\
+             To: <sip:1001@b.example.net>\r\n\
+             Call-ID: loop-test\r\n\
+             CSeq: 1 INVITE\r\n\
+             Content-Length: 0\r\n\r\n",
+            max_forwards
+        );
+        SipRequest::parse(text.as_bytes()).unwrap()
+    }
+
+    #[test]
+    fn test_forward_decrements_and_reuses_branch() {
+        let hops = HopTracker::new("192.0.2.1:5060");
+
+        let forwarded = hops.forward(&invite(None), "sip:1001@10.0.0.2").unwrap();
+        assert_eq!(max_forwards(&forwarded), Some(DEFAULT_MAX_FORWARDS - 1));
+        let forwarded_vias = vias(&forwarded);
+        assert_eq!(forwarded_vias.len(), 2);
+        assert_eq!(forwarded_vias[0].0, "192.0.2.1:5060");
+        let branch = forwarded_vias[0].1.clone().unwrap();
+        assert!(branch.starts_with("z9hG4bK"));
+        assert_ne!(branch, "z9hG4bKcaller");
+
+        // Retransmission: same branch; another target: a new one
+        let again = hops.forward(&invite(None), "sip:1001@10.0.0.2").unwrap();
+        assert_eq!(vias(&again)[0].1.as_deref(), Some(branch.as_str()));
+        let other = hops.forward(&invite(None), "sip:1001@10.0.0.3").unwrap();
+        assert_ne!(vias(&other)[0].1.as_deref(), Some(branch.as_str()));
+
+        assert_eq!(
+            max_forwards(&hops.forward(&invite(Some(5)), "sip:1001@10.0.0.2").unwrap()),
+            Some(4)
+        );
+        assert_eq!(forwarded_max_forwards(&invite(Some(0))), None);
+
+        // Our branch coming back is a loop; someone else's Via is not
+        assert_eq!(hops.check(&forwarded), Some(482));
+        assert_eq!(hops.check(&invite(None)), None);
+    }
+
+    #[tokio::test]
+    async fn test_forwarding_loop_between_two_servers() {
+        // Proxies keeping the Via stack: the INVITE coming back is detected
+        let a = LoopingServer::new("192.0.2.1:5060", false);
+        let b = LoopingServer::new("198.51.100.1:5060", false);
+        a.peer.set(b.clone()).ok();
+        b.peer.set(a.clone()).ok();
+
+        let response = a
+            .forward("sip:1001@b.example.net", &invite(Some(70)))
+            .await
+            .unwrap();
+        assert_eq!(response.status_code(), 482);
+        assert_eq!(a.received.load(Ordering::SeqCst), 2);
+        assert_eq!(b.received.load(Ordering::SeqCst), 1);
+
+        // B2BUAs re-originating the request hide the loop; Max-Forwards ends it
+        let a = LoopingServer::new("192.0.2.1:5060", true);
+        let b = LoopingServer::new("198.51.100.1:5060", true);
+        a.peer.set(b.clone()).ok();
+        b.peer.set(a.clone()).ok();
+
+        let response = a
+            .forward("sip:1001@b.example.net", &invite(Some(10)))
+            .await
+            .unwrap();
+        assert_eq!(response.status_code(), 483);
+        assert_eq!(
+            a.received.load(Ordering::SeqCst) + b.received.load(Ordering::SeqCst),
+            11
+        );
+    }
+}
@@ -0,0 +1,463 @@
+//! SIP INFO handling
+//!
+//! Some gateways relay DTMF as INFO requests (`application/dtmf-relay` or
+//! `application/dtmf`) instead of RFC 2833 telephone-events. Received digits
+//! are published on the same per-call `DtmfDispatcher` stream as RTP
+//! digits. Toward a leg that negotiated neither telephone-event nor a codec
+//! carrying inband tones, digits are sent as INFO.
+
+use super::builder::ResponseBuilder;
+use super::call_router::CallRouter;
+use super::call_state::CallLeg;
+use super::dialog::DialogManager;
+use super::handler::SipHandler;
+use super::message::{SipError, SipMethod, SipRequest, SipResponse};
+use super::sdp::SdpSession;
+use crate::domain::sip_trunk::DtmfMode;
+use crate::infrastructure::ivr::dtmf::{DtmfDispatcher, DtmfEvent, DtmfParser};
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+use tracing::{debug, info, warn};
+
+/// Content type of DTMF sent as INFO
+pub const DTMF_RELAY_CONTENT_TYPE: &str = "application/dtmf-relay";
+
+/// RTP payload types whose audio carries inband DTMF reliably (G.711)
+const INBAND_DTMF_PAYLOAD_TYPES: [u8; 2] = [0, 8];
+
+/// Handling of INFO requests that are not DTMF
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct InfoPolicy {
+    /// Answer other content types with 415 instead of 200
+    pub reject_unsupported: bool,
+}
+
+/// INFO handler
+pub struct InfoHandler {
+    call_router: Arc<CallRouter>,
+    dtmf: Arc<DtmfDispatcher>,
+    policy: InfoPolicy,
+}
+
+impl InfoHandler {
+    pub fn new(call_router: Arc<CallRouter>, dtmf: Arc<DtmfDispatcher>) -> Self {
+        Self {
+            call_router,
+            dtmf,
+            policy: InfoPolicy::default(),
+        }
+    }
+
+    pub fn with_policy(mut self, policy: InfoPolicy) -> Self {
+        self.policy = policy;
+        self
+    }
+
+    fn content_type(request: &SipRequest) -> Option<String> {
+        request.headers().iter().find_map(|h| {
+            let line = h.to_string();
+            let (name, value) = line.split_once(':')?;
+            let name = name.trim();
+            (name.eq_ignore_ascii_case("Content-Type") || name == "c")
+                .then(|| value.trim().to_string())
+        })
+    }
+}
+
+#[async_trait]
+impl SipHandler for InfoHandler {
+    async fn handle_request(&self, request: SipRequest) -> Result<SipResponse, SipError> {
+        let call_id = request.call_id().unwrap_or_else(|| "unknown".to_string());
+
+        // INFO is only valid inside an existing dialog
+        match self.call_router.get_call_state(&call_id).await {
+            Some(state) if state.is_active() => {}
+            _ => {
+                debug!("INFO for unknown call {}", call_id);
+                return ResponseBuilder::new(481).build_for_request(&request);
+            }
+        }
+
+        let body = String::from_utf8_lossy(request.body()).to_string();
+        if body.trim().is_empty() {
+            // Keepalive INFO
+            return ResponseBuilder::ok().build_for_request(&request);
+        }
+
+        let content_type = Self::content_type(&request).unwrap_or_default();
+        match DtmfParser::parse_info_body(&content_type, &body) {
+            Some(Some(event)) => {
+                info!("INFO DTMF '{}' on call {}", event.digit.to_char(), call_id);
+                self.dtmf.publish(&call_id, event);
+            }
+            Some(None) => {
+                warn!("Unparsable {} INFO body on call {}", content_type, call_id);
+            }
+            None if self.policy.reject_unsupported => {
+                debug!("Rejecting {} INFO on call {}", content_type, call_id);
+                return ResponseBuilder::new(415)
+                    .header(rsip::Header::Other(
+                        "Accept".to_string(),
+                        format!("{}, application/dtmf", DTMF_RELAY_CONTENT_TYPE),
+                    ))
+                    .build_for_request(&request);
+            }
+            None => {
+                debug!("Ignoring {} INFO on call {}", content_type, call_id);
+            }
+        }
+
+        ResponseBuilder::ok().build_for_request(&request)
+    }
+
+    fn can_handle(&self, method: SipMethod) -> bool {
+        matches!(method, SipMethod::Info)
+    }
+}
+
+/// Sends in-dialog INFO requests toward a call leg
+#[async_trait]
+pub trait InfoSender: Send + Sync {
+    async fn send_info(
+        &self,
+        call_id: &str,
+        leg: &CallLeg,
+        content_type: &str,
+        body: &str,
+    ) -> Result<(), SipError>;
+}
+
+/// DTMF transport toward a party, from the SDP it sent
+///
+/// Telephone-events when offered, inband tones over G.711, SIP INFO
+/// otherwise.
+pub fn dtmf_mode_for(remote_sdp: &SdpSession) -> DtmfMode {
+    let audio = match remote_sdp.audio_media() {
+        Some(audio) if !audio.is_rejected() => audio,
+        _ => return DtmfMode::SipInfo,
+    };
+
+    let telephone_event = audio.rtpmap.iter().any(|(_, encoding)| {
+        encoding
+            .to_ascii_lowercase()
+            .starts_with("telephone-event/")
+    });
+    if telephone_event {
+        DtmfMode::Rfc2833
+    } else if remote_sdp
+        .audio_codecs()
+        .iter()
+        .any(|pt| INBAND_DTMF_PAYLOAD_TYPES.contains(pt))
+    {
+        DtmfMode::Inband
+    } else {
+        DtmfMode::SipInfo
+    }
+}
+
+/// Sends DTMF toward call legs using the negotiated transport
+pub struct DtmfSender {
+    dialogs: Arc<DialogManager>,
+    info: Arc<dyn InfoSender>,
+}
+
+impl DtmfSender {
+    pub fn new(dialogs: Arc<DialogManager>, info: Arc<dyn InfoSender>) -> Self {
+        Self { dialogs, info }
+    }
+
+    /// Transport for DTMF on a call (SIP INFO when no SDP was negotiated)
+    pub async fn mode(&self, call_id: &str) -> DtmfMode {
+        self.dialogs
+            .get(call_id)
+            .await
+            .and_then(|dialog| dialog.last_remote_sdp().and_then(SdpSession::parse))
+            .map(|sdp| dtmf_mode_for(&sdp))
+            .unwrap_or(DtmfMode::SipInfo)
+    }
+
+    /// Send a digit toward a leg; returns the transport selected
+    ///
+    /// Only SIP INFO is sent here: telephone-events and inband tones are
+    /// generated by the leg's media stream.
+    pub async fn send(
+        &self,
+        call_id: &str,
+        leg: &CallLeg,
+        event: &DtmfEvent,
+    ) -> Result<DtmfMode, SipError> {
+        let mode = self.mode(call_id).await;
+        if mode == DtmfMode::SipInfo {
+            self.info
+                .send_info(
+                    call_id,
+                    leg,
+                    DTMF_RELAY_CONTENT_TYPE,
+                    &DtmfParser::to_sip_info(event),
+                )
+                .await?;
+            debug!(
+                "Sent DTMF '{}' as INFO on call {}",
+                event.digit.to_char(),
+                call_id
+            );
+        }
+        Ok(mode)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::infrastructure::ivr::dtmf::DtmfDigit;
+    use crate::infrastructure::ivr::flow::{IvrFlow, IvrFlowEngine};
+    use crate::infrastructure::ivr::menu::{IvrMenuBuilder, IvrMenuSystem, MenuAction};
+    use crate::infrastructure::protocols::sip::registrar::Registrar;
+    use std::sync::Mutex;
+
+    fn info_request(call_id: &str, content_type: &str, body: &str) -> SipRequest {
+        let request = format!(
+            "INFO sip:ivr@example.com SIP/2.0\r\n\
+             Via: SIP/2.0/UDP 192.0.2.1:5060;branch=z9hG4bKinfo{call_id}\r\n\
+             From: <sip:+15550100@carrier.example>;tag=gw1\r\n\
+             To: <sip:ivr@example.com>;tag=yak1\r\n\
+             Call-ID: {call_id}\r\n\
+             CSeq: 2 INFO\r\n\
+             Content-Type: {content_type}\r\n\
+             Content-Length: {len}\r\n\
+             \r\n\
+             {body}",
+            call_id = call_id,
+            content_type = content_type,
+            len = body.len(),
+            body = body,
+        );
+        SipRequest::parse(request.as_bytes()).unwrap()
+    }
+
+    async fn answered_call(call_id: &str) -> Arc<CallRouter> {
+        let router = Arc::new(CallRouter::new(Arc::new(Registrar::new())));
+        router
+            .create_call(
+                call_id.to_string(),
+                "sip:+15550100@carrier.example".to_string(),
+                "sip:ivr@example.com".to_string(),
+            )
+            .await
+            .unwrap();
+        router.answer_call(call_id).await.unwrap();
+        router
+    }
+
+    fn ivr_flow() -> IvrFlow {
+        let mut menus = IvrMenuSystem::new();
+        menus.add_menu(
+            IvrMenuBuilder::new(
+                "main".to_string(),
+                "Main".to_string(),
+                "main.wav".to_string(),
+            )
+            .add_item(
+                '1',
+                "Sales".to_string(),
+                MenuAction::Transfer("sip:sales@example.com".to_string()),
+            )
+            .add_item(
+                '2',
+                "Support".to_string(),
+                MenuAction::GotoMenu("support".to_string()),
+            )
+            .build(),
+        );
+        menus.add_menu(
+            IvrMenuBuilder::new(
+                "support".to_string(),
+                "Support".to_string(),
+                "support.wav".to_string(),
+            )
+            .add_item(
+                '1',
+                "Technical".to_string(),
+                MenuAction::Transfer("sip:tech@example.com".to_string()),
+            )
+            .build(),
+        );
+        IvrFlow::new(
+            "flow".to_string(),
+            "Flow".to_string(),
+            "main".to_string(),
+            menus,
+        )
+    }
+
+    /// Feed a call's DTMF stream into a fresh IVR session; returns the actions
+    async fn run_ivr(
+        call_id: &str,
+        receiver: &mut tokio::sync::broadcast::Receiver<DtmfEvent>,
+        digits: usize,
+    ) -> Vec<MenuAction> {
+        let flow = ivr_flow();
+        let engine = IvrFlowEngine::new();
+        engine
+            .start_session(call_id.to_string(), &flow)
+            .await
+            .unwrap();
+
+        let mut actions = Vec::new();
+        for _ in 0..digits {
+            let event = receiver.recv().await.unwrap();
+            actions.push(engine.process_dtmf(call_id, event, &flow).await.unwrap());
+        }
+        actions
+    }
+
+    #[tokio::test]
+    async fn test_info_digits_reach_ivr_like_rtp_digits() {
+        let dtmf = Arc::new(DtmfDispatcher::new());
+        let router = answered_call("info-call").await;
+        let handler = InfoHandler::new(router, dtmf.clone());
+
+        // Gateway relaying DTMF as INFO
+        let mut info_digits = dtmf.subscribe("info-call");
+        let relay = info_request(
+            "info-call",
+            "application/dtmf-relay",
+            "Signal=2\r\nDuration=160\r\n",
+        );
+        assert_eq!(
+            handler.handle_request(relay).await.unwrap().status_code(),
+            200
+        );
+        let plain = info_request("info-call", "application/dtmf", "1");
+        assert_eq!(
+            handler.handle_request(plain).await.unwrap().status_code(),
+            200
+        );
+        let via_info = run_ivr("info-call", &mut info_digits, 2).await;
+
+        // Phone sending RFC 2833 telephone-events
+        let mut rtp_digits = dtmf.subscribe("rtp-call");
+        dtmf.publish_rfc2833("rtp-call", 2, 160);
+        dtmf.publish_rfc2833("rtp-call", 1, 160);
+        let via_rtp = run_ivr("rtp-call", &mut rtp_digits, 2).await;
+
+        assert_eq!(
+            via_info,
+            vec![
+                MenuAction::GotoMenu("support".to_string()),
+                MenuAction::Transfer("sip:tech@example.com".to_string()),
+            ]
+        );
+        assert_eq!(via_info, via_rtp);
+    }
+
+    #[tokio::test]
+    async fn test_info_outside_dialog_and_other_content() {
+        let dtmf = Arc::new(DtmfDispatcher::new());
+        let router = answered_call("call-1").await;
+        let mut receiver = dtmf.subscribe("call-1");
+
+        let handler = InfoHandler::new(router.clone(), dtmf.clone());
+        let response = handler
+            .handle_request(info_request("no-such-call", "application/dtmf", "5"))
+            .await
+            .unwrap();
+        assert_eq!(response.status_code(), 481);
+
+        let other = info_request(
+            "call-1",
+            "application/media_control+xml",
+            "<media_control/>",
+        );
+        let response = handler.handle_request(other.clone()).await.unwrap();
+        assert_eq!(response.status_code(), 200);
+
+        let strict = InfoHandler::new(router, dtmf.clone()).with_policy(InfoPolicy {
+            reject_unsupported: true,
+        });
+        let response = strict.handle_request(other).await.unwrap();
+        assert_eq!(response.status_code(), 415);
+        assert!(receiver.try_recv().is_err());
+    }
+
+    #[derive(Default)]
+    struct RecordingInfoSender {
+        sent: Mutex<Vec<(String, String)>>,
+    }
+
+    #[async_trait]
+    impl InfoSender for RecordingInfoSender {
+        async fn send_info(
+            &self,
+            call_id: &str,
+            _leg: &CallLeg,
+            content_type: &str,
+            body: &str,
+        ) -> Result<(), SipError> {
+            assert_eq!(content_type, DTMF_RELAY_CONTENT_TYPE);
+            self.sent
+                .lock()
+                .unwrap()
+                .push((call_id.to_string(), body.to_string()));
+            Ok(())
+        }
+    }
+
+    fn offer(formats: &str, rtpmap: &str) -> String {
+        format!(
+            "v=0\r\no=gw 1 1 IN IP4 192.0.2.1\r\ns=-\r\nc=IN IP4 192.0.2.1\r\nt=0 0\r\n\
+             m=audio 4000 RTP/AVP {}\r\n{}",
+            formats, rtpmap
+        )
+    }
+
+    #[tokio::test]
+    async fn test_dtmf_transport_follows_negotiation() {
+        let dialogs = Arc::new(DialogManager::new());
+        let sender_info = Arc::new(RecordingInfoSender::default());
+        let sender = DtmfSender::new(dialogs.clone(), sender_info.clone());
+        let local = SdpSession::create_audio_session("10.0.0.1".parse().unwrap(), 20000);
+
+        let offers = [
+            (
+                "rfc2833",
+                offer(
+                    "0 101",
+                    "a=rtpmap:0 PCMU/8000\r\na=rtpmap:101 telephone-event/8000\r\n",
+                ),
+            ),
+            ("inband", offer("8", "a=rtpmap:8 PCMA/8000\r\n")),
+            ("info", offer("18", "a=rtpmap:18 G729/8000\r\n")),
+        ];
+        for (call_id, sdp) in &offers {
+            dialogs
+                .with_dialog(call_id, false, |d| d.answer_offer(1, sdp, local.clone()))
+                .await;
+        }
+
+        let digit = DtmfEvent::new(DtmfDigit::Five, std::time::Duration::from_millis(160));
+        let leg = CallLeg::Caller;
+        assert_eq!(
+            sender.send("rfc2833", &leg, &digit).await.unwrap(),
+            DtmfMode::Rfc2833
+        );
+        assert_eq!(
+            sender.send("inband", &leg, &digit).await.unwrap(),
+            DtmfMode::Inband
+        );
+        assert_eq!(
+            sender.send("info", &leg, &digit).await.unwrap(),
+            DtmfMode::SipInfo
+        );
+
+        assert_eq!(
+            *sender_info.sent.lock().unwrap(),
+            vec![(
+                "info".to_string(),
+                "Signal=5\r\nDuration=160\r\n".to_string()
+            )]
+        );
+    }
+}
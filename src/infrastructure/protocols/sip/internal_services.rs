@@ -0,0 +1,506 @@
+//! Built-in test services
+//!
+//! Installers check a phone's audio path without a second person by dialing
+//! a test service: the echo test sends every packet it receives back after a
+//! configurable delay, the milliwatt test plays a steady 1004 Hz tone and
+//! the readback test records a few seconds of the caller and plays them
+//! back. [`InternalServiceHandler`] answers calls to their extensions itself,
+//! without a registrar lookup. Each service has a maximum call duration and
+//! a cap on concurrent calls so it cannot be used to hold lines open, and
+//! calls to it are recorded as test calls, which are never billed.
+
+use super::call_router::CallRouter;
+use crate::domain::routing::simulation::{Explain, Explanation, RouteOutcome, RoutedCall};
+use crate::infrastructure::media::{G711Type, MediaStream, PacketFormat, RtpPacket, ToneGenerator};
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, VecDeque};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::sync::broadcast::{self, error::RecvError};
+use tokio::task::JoinHandle;
+use tokio::time::Instant;
+use tracing::{debug, info, warn};
+
+/// Frequency of the milliwatt test tone
+const MILLIWATT_FREQUENCY: f32 = 1004.0;
+/// Amplitude of the milliwatt test tone, close to 0 dBm0
+const MILLIWATT_AMPLITUDE: f32 = 0.7;
+/// Sample rate of generated audio
+const SAMPLE_RATE: u32 = 8000;
+
+/// A built-in test service
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum InternalService {
+    /// Sends received audio back after a delay
+    Echo,
+    /// Plays a continuous 1004 Hz tone
+    Milliwatt,
+    /// Records the caller, then plays the recording back
+    Readback,
+}
+
+impl InternalService {
+    pub const ALL: [InternalService; 3] = [
+        InternalService::Echo,
+        InternalService::Milliwatt,
+        InternalService::Readback,
+    ];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            InternalService::Echo => "echo",
+            InternalService::Milliwatt => "milliwatt",
+            InternalService::Readback => "readback",
+        }
+    }
+}
+
+/// Limits of one test service
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ServiceLimits {
+    /// Seconds before the service hangs up (0 = no limit)
+    pub max_duration_secs: u64,
+    /// Calls the service takes at once; further calls are answered busy
+    pub max_concurrent: usize,
+}
+
+impl Default for ServiceLimits {
+    fn default() -> Self {
+        Self {
+            max_duration_secs: 300,
+            max_concurrent: 4,
+        }
+    }
+}
+
+/// Extensions, limits and behavior of the test services
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct InternalServicesConfig {
+    pub enabled: bool,
+    /// Extension of each service; an empty one disables the service
+    pub echo_extension: String,
+    pub milliwatt_extension: String,
+    pub readback_extension: String,
+    pub echo: ServiceLimits,
+    pub milliwatt: ServiceLimits,
+    pub readback: ServiceLimits,
+    /// Milliseconds the echo test holds audio before sending it back
+    pub echo_delay_ms: u64,
+    /// Seconds of the caller the readback test records
+    pub readback_record_secs: u64,
+}
+
+impl Default for InternalServicesConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            echo_extension: "9196".to_string(),
+            milliwatt_extension: "9197".to_string(),
+            readback_extension: "9198".to_string(),
+            echo: ServiceLimits::default(),
+            milliwatt: ServiceLimits::default(),
+            readback: ServiceLimits::default(),
+            echo_delay_ms: 0,
+            readback_record_secs: 5,
+        }
+    }
+}
+
+impl InternalServicesConfig {
+    /// Extension dialed to reach `service`
+    pub fn extension(&self, service: InternalService) -> &str {
+        match service {
+            InternalService::Echo => &self.echo_extension,
+            InternalService::Milliwatt => &self.milliwatt_extension,
+            InternalService::Readback => &self.readback_extension,
+        }
+    }
+
+    pub fn limits(&self, service: InternalService) -> &ServiceLimits {
+        match service {
+            InternalService::Echo => &self.echo,
+            InternalService::Milliwatt => &self.milliwatt,
+            InternalService::Readback => &self.readback,
+        }
+    }
+}
+
+/// Why a test service did not take a call
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum InternalServiceError {
+    /// The service already has its maximum number of calls
+    Busy(InternalService),
+    /// No call was reserved under this Call-ID
+    NotReserved,
+}
+
+impl InternalServiceError {
+    /// Final response to the INVITE
+    pub fn status_code(&self) -> u16 {
+        match self {
+            InternalServiceError::Busy(_) => 486,
+            InternalServiceError::NotReserved => 500,
+        }
+    }
+}
+
+impl std::fmt::Display for InternalServiceError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            InternalServiceError::Busy(service) => {
+                write!(
+                    f,
+                    "{} test has its maximum number of calls",
+                    service.as_str()
+                )
+            }
+            InternalServiceError::NotReserved => write!(f, "no test call reserved"),
+        }
+    }
+}
+
+/// A call taken by a test service
+struct ServiceCall {
+    service: InternalService,
+    task: Option<JoinHandle<()>>,
+}
+
+/// Answers calls to the built-in test services
+pub struct InternalServiceHandler {
+    config: InternalServicesConfig,
+    calls: Arc<Mutex<HashMap<String, ServiceCall>>>,
+}
+
+impl InternalServiceHandler {
+    pub fn new(config: InternalServicesConfig) -> Self {
+        Self {
+            config,
+            calls: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+
+    pub fn config(&self) -> &InternalServicesConfig {
+        &self.config
+    }
+
+    /// Service dialed by `to_uri`, if any
+    pub fn service_for(&self, to_uri: &str) -> Option<InternalService> {
+        if !self.config.enabled {
+            return None;
+        }
+        let dialed = CallRouter::extract_username(to_uri);
+        InternalService::ALL.into_iter().find(|service| {
+            let extension = self.config.extension(*service);
+            !extension.is_empty() && extension == dialed
+        })
+    }
+
+    /// Take a place for the call among the service's concurrent calls
+    pub fn reserve(
+        &self,
+        call_id: &str,
+        service: InternalService,
+    ) -> Result<(), InternalServiceError> {
+        let mut calls = self.calls.lock().unwrap();
+        let active = calls
+            .values()
+            .filter(|call| call.service == service)
+            .count();
+        if active >= self.config.limits(service).max_concurrent {
+            return Err(InternalServiceError::Busy(service));
+        }
+        calls.insert(
+            call_id.to_string(),
+            ServiceCall {
+                service,
+                task: None,
+            },
+        );
+        Ok(())
+    }
+
+    /// Run the reserved service on the call's answered `stream`
+    ///
+    /// When the service is done, or reaches its maximum duration, the call
+    /// is ended through `router`. A call ended by the caller stops the
+    /// service once its stream is closed.
+    pub fn start(
+        &self,
+        router: Arc<CallRouter>,
+        call_id: &str,
+        stream: Arc<MediaStream>,
+        format: PacketFormat,
+    ) -> Result<(), InternalServiceError> {
+        let service = match self.calls.lock().unwrap().get(call_id) {
+            Some(call) => call.service,
+            None => return Err(InternalServiceError::NotReserved),
+        };
+        // Subscribed before the caller can get the answer and send audio
+        let received = stream.subscribe();
+        let max_duration = self.config.limits(service).max_duration_secs;
+        let echo_delay = Duration::from_millis(self.config.echo_delay_ms);
+        let record_for = Duration::from_secs(self.config.readback_record_secs);
+
+        let calls = self.calls.clone();
+        let owned_call_id = call_id.to_string();
+        let task = tokio::spawn(async move {
+            let call_id = owned_call_id;
+            let run = async {
+                match service {
+                    InternalService::Echo => echo(&stream, received, format, echo_delay).await,
+                    InternalService::Milliwatt => milliwatt(&stream, format).await,
+                    InternalService::Readback => {
+                        readback(&stream, received, format, record_for).await
+                    }
+                }
+            };
+            if max_duration == 0 {
+                run.await;
+            } else if tokio::time::timeout(Duration::from_secs(max_duration), run)
+                .await
+                .is_err()
+            {
+                info!(
+                    "Call {} reached the {} test's maximum duration of {}s",
+                    call_id,
+                    service.as_str(),
+                    max_duration
+                );
+            }
+
+            calls.lock().unwrap().remove(&call_id);
+            if !stream.is_closed() {
+                if let Err(e) = router.terminate_call(&call_id).await {
+                    debug!("Test call {} already gone: {}", call_id, e);
+                }
+            }
+        });
+
+        match self.calls.lock().unwrap().get_mut(call_id) {
+            Some(call) => call.task = Some(task),
+            // Already finished
+            None => drop(task),
+        }
+        info!(
+            "Call {} connected to the {} test",
+            call_id,
+            service.as_str()
+        );
+        Ok(())
+    }
+
+    /// Stop the service of a call and free its place
+    pub fn release(&self, call_id: &str) -> bool {
+        match self.calls.lock().unwrap().remove(call_id) {
+            Some(call) => {
+                if let Some(task) = call.task {
+                    task.abort();
+                }
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Calls the service is taking
+    pub fn active_calls(&self, service: InternalService) -> usize {
+        self.calls
+            .lock()
+            .unwrap()
+            .values()
+            .filter(|call| call.service == service)
+            .count()
+    }
+}
+
+#[async_trait]
+impl Explain for InternalServiceHandler {
+    fn step(&self) -> &'static str {
+        "internal_service"
+    }
+
+    async fn explain(&self, call: &RoutedCall) -> Option<Explanation> {
+        let service = self.service_for(&call.target_uri)?;
+        Some(
+            Explanation::matched(format!(
+                "{} test service on {}",
+                service.as_str(),
+                self.config.extension(service)
+            ))
+            .ending(RouteOutcome::Answered {
+                by: service.as_str().to_string(),
+            }),
+        )
+    }
+}
+
+impl Drop for InternalServiceHandler {
+    fn drop(&mut self) {
+        for (_, call) in self.calls.lock().unwrap().drain() {
+            if let Some(task) = call.task {
+                task.abort();
+            }
+        }
+    }
+}
+
+/// Interval at which a closed stream is noticed
+fn close_check(format: PacketFormat) -> tokio::time::Interval {
+    tokio::time::interval(Duration::from_millis(format.ptime_ms as u64))
+}
+
+/// Send every received packet back `delay` after it arrived
+async fn echo(
+    stream: &MediaStream,
+    mut received: broadcast::Receiver<RtpPacket>,
+    format: PacketFormat,
+    delay: Duration,
+) {
+    let mut pending: VecDeque<(Instant, RtpPacket)> = VecDeque::new();
+    let mut check = close_check(format);
+    while !stream.is_closed() {
+        let next_due = pending.front().map(|(due, _)| *due);
+        tokio::select! {
+            packet = received.recv() => match packet {
+                Ok(packet) => pending.push_back((Instant::now() + delay, packet)),
+                Err(RecvError::Lagged(skipped)) => warn!("Echo test skipped {} packets", skipped),
+                Err(RecvError::Closed) => break,
+            },
+            _ = tokio::time::sleep_until(next_due.unwrap_or_else(Instant::now)), if next_due.is_some() => {
+                let now = Instant::now();
+                while pending.front().is_some_and(|(due, _)| *due <= now) {
+                    let (_, packet) = pending.pop_front().unwrap();
+                    if let Err(e) = stream.send_rtp(packet.payload, packet.timestamp, packet.marker).await {
+                        warn!("Failed to send echo: {}", e);
+                        return;
+                    }
+                }
+            },
+            _ = check.tick() => {}
+        }
+    }
+}
+
+/// Play the milliwatt tone until the call ends
+async fn milliwatt(stream: &MediaStream, format: PacketFormat) {
+    let tone = ToneGenerator::new(MILLIWATT_FREQUENCY, SAMPLE_RATE, MILLIWATT_AMPLITUDE);
+    let codec = G711Type::from_payload_type(format.payload_type).unwrap_or(G711Type::PCMU);
+    let frame_samples = (SAMPLE_RATE * format.ptime_ms / 1000) as usize;
+    let mut interval = close_check(format);
+    let mut timestamp = 0u32;
+    let mut marker = true;
+    while !stream.is_closed() {
+        interval.tick().await;
+        let samples = tone.generate_samples(frame_samples).await;
+        if let Err(e) = stream
+            .send_rtp(codec.encode(&samples), timestamp, marker)
+            .await
+        {
+            warn!("Failed to send milliwatt tone: {}", e);
+            return;
+        }
+        timestamp = timestamp.wrapping_add(frame_samples as u32);
+        marker = false;
+    }
+}
+
+/// Record the caller for `record_for`, then play the recording back
+async fn readback(
+    stream: &MediaStream,
+    mut received: broadcast::Receiver<RtpPacket>,
+    format: PacketFormat,
+    record_for: Duration,
+) {
+    let mut recording = Vec::new();
+    let mut check = close_check(format);
+    let end = tokio::time::sleep(record_for);
+    tokio::pin!(end);
+    loop {
+        tokio::select! {
+            packet = received.recv() => match packet {
+                Ok(packet) => recording.push(packet),
+                Err(RecvError::Lagged(skipped)) => warn!("Readback test skipped {} packets", skipped),
+                Err(RecvError::Closed) => return,
+            },
+            _ = &mut end => break,
+            _ = check.tick() => {
+                if stream.is_closed() {
+                    return;
+                }
+            }
+        }
+    }
+    drop(received);
+    debug!("Readback test recorded {} packets", recording.len());
+
+    let mut interval = close_check(format);
+    for (index, packet) in recording.into_iter().enumerate() {
+        interval.tick().await;
+        if stream.is_closed() {
+            return;
+        }
+        if let Err(e) = stream
+            .send_rtp(packet.payload, packet.timestamp, index == 0)
+            .await
+        {
+            warn!("Failed to play back recording: {}", e);
+            return;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_service_for_extension() {
+        let mut config = InternalServicesConfig {
+            enabled: true,
+            ..Default::default()
+        };
+        config.readback_extension.clear();
+        let services = InternalServiceHandler::new(config);
+
+        assert_eq!(
+            services.service_for("sip:9196@example.com"),
+            Some(InternalService::Echo)
+        );
+        assert_eq!(
+            services.service_for("sip:9197@example.com"),
+            Some(InternalService::Milliwatt)
+        );
+        assert_eq!(services.service_for("sip:9198@example.com"), None);
+        assert_eq!(services.service_for("sip:bob@example.com"), None);
+
+        let disabled = InternalServiceHandler::new(InternalServicesConfig::default());
+        assert_eq!(disabled.service_for("sip:9196@example.com"), None);
+    }
+
+    #[test]
+    fn test_concurrent_call_cap() {
+        let mut config = InternalServicesConfig {
+            enabled: true,
+            ..Default::default()
+        };
+        config.echo.max_concurrent = 1;
+        let services = InternalServiceHandler::new(config);
+
+        services.reserve("echo-1", InternalService::Echo).unwrap();
+        assert_eq!(
+            services.reserve("echo-2", InternalService::Echo),
+            Err(InternalServiceError::Busy(InternalService::Echo))
+        );
+        services
+            .reserve("tone-1", InternalService::Milliwatt)
+            .unwrap();
+        assert_eq!(services.active_calls(InternalService::Echo), 1);
+
+        assert!(services.release("echo-1"));
+        services.reserve("echo-2", InternalService::Echo).unwrap();
+    }
+}
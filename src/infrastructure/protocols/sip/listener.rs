@@ -0,0 +1,243 @@
+//! Runtime lifecycle of SIP listeners
+//!
+//! Listeners are added and removed while the server runs, so a bind
+//! address or port change does not need a restart. A removed listener
+//! drains first: it takes no new flows (no new TCP/TLS connections, 503
+//! to UDP requests outside its dialogs and transactions) but keeps serving
+//! the dialogs established on it until they end or the drain timeout
+//! passes, and only then closes.
+
+use super::connection::ConnectionTable;
+use super::message::SipError;
+use super::transport::{TcpTransport, TlsTransport, Transport, TransportProtocol, UdpTransport};
+use serde::Serialize;
+use std::collections::{HashMap, HashSet};
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use tokio::net::UdpSocket;
+
+/// Protocol and configured bind address of a listener
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize)]
+pub struct ListenerSpec {
+    pub protocol: TransportProtocol,
+    pub bind: SocketAddr,
+}
+
+impl ListenerSpec {
+    pub fn new(protocol: TransportProtocol, bind: SocketAddr) -> Self {
+        Self { protocol, bind }
+    }
+}
+
+/// Whether a listener takes new flows
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ListenerState {
+    Active,
+    /// Serving its remaining dialogs before closing
+    Draining,
+}
+
+/// A running listener
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct ListenerInfo {
+    #[serde(flatten)]
+    pub spec: ListenerSpec,
+    pub local_addr: SocketAddr,
+    pub state: ListenerState,
+    /// Dialogs established on the listener
+    pub dialogs: usize,
+}
+
+/// What a reload did to one listener
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(tag = "outcome", rename_all = "snake_case")]
+pub enum ListenerOutcome {
+    /// Bound and serving
+    Added { local_addr: SocketAddr },
+    /// Already serving, left alone
+    Kept { local_addr: SocketAddr },
+    /// No longer configured; closes once its dialogs end
+    Draining { local_addr: SocketAddr, dialogs: usize },
+    /// Could not be bound; the rest of the reload went ahead
+    Failed { error: String },
+}
+
+/// Outcome of a reload for one listener
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct ListenerReport {
+    #[serde(flatten)]
+    pub spec: ListenerSpec,
+    #[serde(flatten)]
+    pub outcome: ListenerOutcome,
+}
+
+/// Dialogs and transactions running over one listener
+#[derive(Debug, Default)]
+pub struct ListenerActivity {
+    draining: AtomicBool,
+    /// Call-IDs of dialogs established over the listener
+    dialogs: Mutex<HashSet<String>>,
+    /// Requests being handled, by Call-ID
+    transactions: Mutex<HashMap<String, usize>>,
+}
+
+impl ListenerActivity {
+    pub fn is_draining(&self) -> bool {
+        self.draining.load(Ordering::SeqCst)
+    }
+
+    pub fn set_draining(&self, draining: bool) {
+        self.draining.store(draining, Ordering::SeqCst);
+    }
+
+    /// A draining listener still serves requests of its dialogs and
+    /// transactions; everything else is new
+    pub fn accepts(&self, call_id: Option<&str>) -> bool {
+        if !self.is_draining() {
+            return true;
+        }
+        call_id.is_some_and(|call_id| {
+            self.dialogs.lock().unwrap().contains(call_id)
+                || self.transactions.lock().unwrap().contains_key(call_id)
+        })
+    }
+
+    pub fn attach_dialog(&self, call_id: &str) {
+        self.dialogs.lock().unwrap().insert(call_id.to_string());
+    }
+
+    pub fn detach_dialog(&self, call_id: &str) {
+        self.dialogs.lock().unwrap().remove(call_id);
+    }
+
+    pub fn has_dialog(&self, call_id: &str) -> bool {
+        self.dialogs.lock().unwrap().contains(call_id)
+    }
+
+    pub fn dialog_count(&self) -> usize {
+        self.dialogs.lock().unwrap().len()
+    }
+
+    /// A request of `call_id` is being handled until the guard drops
+    pub fn begin_transaction(self: &Arc<Self>, call_id: &str) -> TransactionGuard {
+        *self
+            .transactions
+            .lock()
+            .unwrap()
+            .entry(call_id.to_string())
+            .or_default() += 1;
+        TransactionGuard {
+            activity: self.clone(),
+            call_id: call_id.to_string(),
+        }
+    }
+
+    /// No dialog or transaction is left
+    pub fn is_idle(&self) -> bool {
+        self.dialogs.lock().unwrap().is_empty() && self.transactions.lock().unwrap().is_empty()
+    }
+}
+
+/// A request in progress on a listener
+pub struct TransactionGuard {
+    activity: Arc<ListenerActivity>,
+    call_id: String,
+}
+
+impl Drop for TransactionGuard {
+    fn drop(&mut self) {
+        let mut transactions = self.activity.transactions.lock().unwrap();
+        if let Some(count) = transactions.get_mut(&self.call_id) {
+            *count -= 1;
+            if *count == 0 {
+                transactions.remove(&self.call_id);
+            }
+        }
+    }
+}
+
+/// Where a listener's outbound messages leave
+#[derive(Clone)]
+pub(crate) enum ListenerRoute {
+    Udp(Arc<UdpSocket>),
+    Tcp(Arc<ConnectionTable>),
+    /// TLS listeners only receive
+    None,
+}
+
+pub(crate) enum ListenerTransport {
+    Udp(UdpTransport),
+    Tcp(TcpTransport),
+    Tls(TlsTransport),
+}
+
+impl ListenerTransport {
+    /// Stop accepting new flows; open TCP connections are kept
+    pub(crate) async fn stop(&mut self) -> Result<(), SipError> {
+        match self {
+            ListenerTransport::Udp(transport) => transport.stop().await,
+            ListenerTransport::Tcp(transport) => transport.stop().await,
+            ListenerTransport::Tls(transport) => transport.stop().await,
+        }
+    }
+}
+
+/// A started listener
+pub(crate) struct Listener {
+    pub spec: ListenerSpec,
+    pub local_addr: SocketAddr,
+    pub route: ListenerRoute,
+    pub activity: Arc<ListenerActivity>,
+    pub transport: ListenerTransport,
+}
+
+impl Listener {
+    pub fn info(&self) -> ListenerInfo {
+        ListenerInfo {
+            spec: self.spec,
+            local_addr: self.local_addr,
+            state: if self.activity.is_draining() {
+                ListenerState::Draining
+            } else {
+                ListenerState::Active
+            },
+            dialogs: self.activity.dialog_count(),
+        }
+    }
+
+    /// Stop the transport and drop the connections still open on it
+    pub async fn close(mut self) -> Result<(), SipError> {
+        self.transport.stop().await?;
+        if let ListenerRoute::Tcp(connections) = &self.route {
+            connections.close_all().await;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_draining_listener_accepts_only_its_dialogs_and_transactions() {
+        let activity = Arc::new(ListenerActivity::default());
+        assert!(activity.accepts(None));
+
+        activity.attach_dialog("call-1");
+        let transaction = activity.begin_transaction("call-2");
+        activity.set_draining(true);
+        assert!(activity.accepts(Some("call-1")));
+        assert!(activity.accepts(Some("call-2")));
+        assert!(!activity.accepts(Some("call-3")));
+        assert!(!activity.accepts(None));
+        assert!(!activity.is_idle());
+
+        drop(transaction);
+        assert!(!activity.accepts(Some("call-2")));
+        activity.detach_dialog("call-1");
+        assert!(activity.is_idle());
+    }
+}
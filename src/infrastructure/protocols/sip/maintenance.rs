@@ -0,0 +1,774 @@
+//! Scoped maintenance mode
+//!
+//! Takes one tenant (by SIP realm) or one trunk (by its name in the header
+//! rules, which know its hosts) out of service without touching the rest
+//! of the system. While a scope is in maintenance, new calls involving it
+//! are refused with 503; internal callers hear the scope's announcement
+//! instead when one is set. Established calls continue, and REGISTERs from
+//! a maintained tenant are still accepted but flagged. Clearing maintenance
+//! restores normal routing at once.
+//!
+//! With a drain deadline, [`MaintenanceDrainer`] ends the calls still up in
+//! the scope at the deadline: the parties get a BYE and the calls end with
+//! [`EndReason::Maintenance`].
+
+use super::call_router::{CallCheckpoint, CallRouter};
+use super::header_rules::HeaderRulesEngine;
+use super::redirect::uri_host;
+use super::transport::{OutgoingMessage, TransportProtocol};
+use crate::domain::call::EndReason;
+use crate::domain::routing::simulation::{Explain, Explanation, RouteOutcome, RoutedCall};
+use crate::domain::tenant_branding::realm_of;
+use async_trait::async_trait;
+use bytes::Bytes;
+use chrono::{DateTime, Utc};
+use metrics::{counter, gauge};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fmt;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::{Arc, Mutex};
+use tokio::sync::{broadcast, mpsc};
+use tokio::task::JoinHandle;
+use tracing::{debug, info, warn};
+use uuid::Uuid;
+
+/// Seconds an internal caller refused for maintenance hears the scope's
+/// announcement before the call is ended
+pub const ANNOUNCEMENT_SECS: u64 = 10;
+
+/// Tenant or trunk taken out of service
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(tag = "kind", content = "id", rename_all = "snake_case")]
+pub enum MaintenanceScope {
+    /// Tenant by SIP realm
+    Tenant(String),
+    /// Trunk by name
+    Trunk(String),
+}
+
+impl MaintenanceScope {
+    pub fn kind(&self) -> &'static str {
+        match self {
+            MaintenanceScope::Tenant(_) => "tenant",
+            MaintenanceScope::Trunk(_) => "trunk",
+        }
+    }
+}
+
+impl fmt::Display for MaintenanceScope {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MaintenanceScope::Tenant(realm) => write!(f, "tenant {}", realm),
+            MaintenanceScope::Trunk(name) => write!(f, "trunk {}", name),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MaintenanceError {
+    /// No trunk of that name in the header rules, so none of its calls
+    /// could be recognized
+    UnknownTrunk(String),
+}
+
+impl fmt::Display for MaintenanceError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MaintenanceError::UnknownTrunk(name) => {
+                write!(f, "Trunk {} has no hosts in the header rules", name)
+            }
+        }
+    }
+}
+
+impl std::error::Error for MaintenanceError {}
+
+/// Maintenance change requested through the API
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct MaintenanceRequest {
+    pub enabled: bool,
+    /// Calls still up in the scope at this time are ended
+    #[serde(default)]
+    pub drain_deadline: Option<DateTime<Utc>>,
+    /// Audio file played to internal callers instead of a bare 503
+    #[serde(default)]
+    pub announcement: Option<String>,
+}
+
+/// Maintenance state of a scope
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ScopeMaintenance {
+    pub scope: MaintenanceScope,
+    pub since: DateTime<Utc>,
+    pub drain_deadline: Option<DateTime<Utc>>,
+    pub announcement: Option<String>,
+    /// New calls refused since maintenance started
+    pub rejected_calls: u64,
+    /// REGISTERs accepted from the tenant since maintenance started
+    pub flagged_registrations: u64,
+}
+
+/// What happened to a scope
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum MaintenanceChange {
+    /// Maintenance started, or its deadline or announcement changed
+    Enabled,
+    /// Maintenance cleared; routing is back to normal
+    Cleared,
+    /// The drain deadline passed and the remaining calls were ended
+    Drained,
+}
+
+/// Maintenance change, published to subscribers
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MaintenanceEvent {
+    pub scope: MaintenanceScope,
+    pub change: MaintenanceChange,
+    pub drain_deadline: Option<DateTime<Utc>>,
+    /// Calls ended by a drain
+    pub terminated_calls: usize,
+    pub timestamp: DateTime<Utc>,
+}
+
+/// Scopes in maintenance, consulted for new calls and registrations
+pub struct MaintenanceRegistry {
+    scopes: Mutex<HashMap<MaintenanceScope, ScopeMaintenance>>,
+    /// Trunks by host, to recognize their calls
+    header_rules: Option<Arc<HeaderRulesEngine>>,
+    events: broadcast::Sender<MaintenanceEvent>,
+}
+
+impl MaintenanceRegistry {
+    pub fn new() -> Self {
+        let (events, _) = broadcast::channel(64);
+        Self {
+            scopes: Mutex::new(HashMap::new()),
+            header_rules: None,
+            events,
+        }
+    }
+
+    /// Recognize trunk calls by the hosts of the trunks in the header rules
+    pub fn with_header_rules(mut self, header_rules: Arc<HeaderRulesEngine>) -> Self {
+        self.header_rules = Some(header_rules);
+        self
+    }
+
+    /// Maintenance changes
+    pub fn subscribe(&self) -> broadcast::Receiver<MaintenanceEvent> {
+        self.events.subscribe()
+    }
+
+    /// Put a scope into maintenance, or clear it
+    ///
+    /// Enabling a scope already in maintenance updates its deadline and
+    /// announcement. Returns the scope's state when enabled.
+    pub fn set(
+        &self,
+        scope: MaintenanceScope,
+        request: MaintenanceRequest,
+    ) -> Result<Option<ScopeMaintenance>, MaintenanceError> {
+        if let MaintenanceScope::Trunk(name) = &scope {
+            let known = self
+                .header_rules
+                .as_ref()
+                .is_some_and(|rules| rules.config().trunks.contains_key(name));
+            if request.enabled && !known {
+                return Err(MaintenanceError::UnknownTrunk(name.clone()));
+            }
+        }
+
+        let (state, count) = {
+            let mut scopes = self.scopes.lock().unwrap();
+            let state = if request.enabled {
+                let state = scopes
+                    .entry(scope.clone())
+                    .or_insert_with(|| ScopeMaintenance {
+                        scope: scope.clone(),
+                        since: Utc::now(),
+                        drain_deadline: None,
+                        announcement: None,
+                        rejected_calls: 0,
+                        flagged_registrations: 0,
+                    });
+                state.drain_deadline = request.drain_deadline;
+                state.announcement = request.announcement;
+                Some(state.clone())
+            } else {
+                if scopes.remove(&scope).is_none() {
+                    return Ok(None);
+                }
+                None
+            };
+            (state, scopes.len())
+        };
+        gauge!("maintenance_scopes").set(count as f64);
+
+        let change = match &state {
+            Some(state) => {
+                info!(
+                    "{} in maintenance (drain deadline {:?})",
+                    scope, state.drain_deadline
+                );
+                MaintenanceChange::Enabled
+            }
+            None => {
+                info!("{} out of maintenance", scope);
+                MaintenanceChange::Cleared
+            }
+        };
+        self.publish(MaintenanceEvent {
+            drain_deadline: state.as_ref().and_then(|s| s.drain_deadline),
+            scope,
+            change,
+            terminated_calls: 0,
+            timestamp: Utc::now(),
+        });
+        Ok(state)
+    }
+
+    pub fn get(&self, scope: &MaintenanceScope) -> Option<ScopeMaintenance> {
+        self.scopes.lock().unwrap().get(scope).cloned()
+    }
+
+    /// Every scope in maintenance, tenants first
+    pub fn list(&self) -> Vec<ScopeMaintenance> {
+        let mut scopes: Vec<ScopeMaintenance> =
+            self.scopes.lock().unwrap().values().cloned().collect();
+        scopes.sort_by(|a, b| a.scope.cmp(&b.scope));
+        scopes
+    }
+
+    /// Name of the trunk with host `host`
+    pub fn trunk_of(&self, host: &str) -> Option<String> {
+        self.header_rules
+            .as_ref()?
+            .trunk_of(host)
+            .map(str::to_string)
+    }
+
+    /// Tenants and trunks a call between `caller_uri` and `callee_uri`
+    /// involves; `peers` are the addresses its legs come from or go to
+    pub fn scopes_of(
+        &self,
+        caller_uri: &str,
+        callee_uri: &str,
+        peers: &[IpAddr],
+    ) -> Vec<MaintenanceScope> {
+        let mut scopes = Vec::new();
+        for realm in [realm_of(caller_uri), realm_of(callee_uri)].into_iter().flatten() {
+            scopes.push(MaintenanceScope::Tenant(realm.to_ascii_lowercase()));
+        }
+        let hosts = peers
+            .iter()
+            .map(IpAddr::to_string)
+            .chain(std::iter::once(uri_host(callee_uri).to_string()));
+        for host in hosts {
+            if let Some(trunk) = self.trunk_of(&host) {
+                scopes.push(MaintenanceScope::Trunk(trunk));
+            }
+        }
+        scopes.dedup();
+        scopes
+    }
+
+    /// Scope in maintenance that refuses a new call, among `scopes`
+    fn refusing_scope(
+        &self,
+        scopes: &HashMap<MaintenanceScope, ScopeMaintenance>,
+        caller_uri: &str,
+        callee_uri: &str,
+        source: Option<IpAddr>,
+    ) -> Option<MaintenanceScope> {
+        if scopes.is_empty() {
+            return None;
+        }
+        let peers: Vec<IpAddr> = source.into_iter().collect();
+        self.scopes_of(caller_uri, callee_uri, &peers)
+            .into_iter()
+            .find(|scope| scopes.contains_key(scope))
+    }
+
+    /// Maintenance that would refuse a new call, without counting it
+    pub fn would_refuse(
+        &self,
+        caller_uri: &str,
+        callee_uri: &str,
+        source: Option<IpAddr>,
+    ) -> Option<ScopeMaintenance> {
+        let scopes = self.scopes.lock().unwrap();
+        let scope = self.refusing_scope(&scopes, caller_uri, callee_uri, source)?;
+        scopes.get(&scope).cloned()
+    }
+
+    /// Maintenance refusing a new call, counted as a rejection
+    pub fn check_call(
+        &self,
+        caller_uri: &str,
+        callee_uri: &str,
+        source: Option<IpAddr>,
+    ) -> Option<ScopeMaintenance> {
+        let mut scopes = self.scopes.lock().unwrap();
+        let scope = self.refusing_scope(&scopes, caller_uri, callee_uri, source)?;
+        let state = scopes.get_mut(&scope)?;
+        state.rejected_calls += 1;
+        counter!("maintenance_rejected_calls_total", "scope" => scope.kind()).increment(1);
+        Some(state.clone())
+    }
+
+    /// Whether a REGISTER for `aor` comes from a tenant in maintenance;
+    /// counted as a flagged registration
+    pub fn flag_registration(&self, aor: &str) -> bool {
+        let Some(realm) = realm_of(aor) else {
+            return false;
+        };
+        let scope = MaintenanceScope::Tenant(realm.to_ascii_lowercase());
+        let mut scopes = self.scopes.lock().unwrap();
+        match scopes.get_mut(&scope) {
+            Some(state) => {
+                state.flagged_registrations += 1;
+                counter!("maintenance_flagged_registrations_total").increment(1);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Whether an established call involves `scope`
+    fn involves(&self, scope: &MaintenanceScope, call: &CallCheckpoint) -> bool {
+        let peers: Vec<IpAddr> = [call.caller_contact, call.callee_contact]
+            .into_iter()
+            .flatten()
+            .map(|addr| addr.ip())
+            .collect();
+        self.scopes_of(&call.caller_uri, &call.callee_uri, &peers)
+            .contains(scope)
+    }
+
+    fn publish(&self, event: MaintenanceEvent) {
+        // No subscribers is fine
+        let _ = self.events.send(event);
+    }
+}
+
+impl Default for MaintenanceRegistry {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Ends the calls of scopes whose drain deadline passed
+pub struct MaintenanceDrainer {
+    registry: Arc<MaintenanceRegistry>,
+    call_router: Arc<CallRouter>,
+    /// Where BYEs go out, with the address put in their Via
+    outbound: Option<(mpsc::Sender<OutgoingMessage>, String)>,
+    cseq: AtomicU32,
+}
+
+impl MaintenanceDrainer {
+    pub fn new(registry: Arc<MaintenanceRegistry>, call_router: Arc<CallRouter>) -> Self {
+        Self {
+            registry,
+            call_router,
+            outbound: None,
+            cseq: AtomicU32::new(1),
+        }
+    }
+
+    /// Send BYEs to the parties of drained calls, with `local_addr` as the
+    /// Via sent-by (without, calls are only ended locally)
+    pub fn with_outbound(mut self, outbound: mpsc::Sender<OutgoingMessage>, local_addr: String) -> Self {
+        self.outbound = Some((outbound, local_addr));
+        self
+    }
+
+    /// End every call involving `scope`; returns how many were ended
+    pub async fn drain(&self, scope: &MaintenanceScope) -> usize {
+        let calls: Vec<CallCheckpoint> = self
+            .call_router
+            .checkpoints()
+            .await
+            .into_iter()
+            .filter(|call| self.registry.involves(scope, call))
+            .collect();
+
+        let mut terminated = 0;
+        for call in calls {
+            self.send_byes(&call).await;
+            match self.call_router.end_call(&call.call_id, EndReason::Maintenance).await {
+                Ok(()) => terminated += 1,
+                Err(e) => debug!("Call {} gone before the drain: {}", call.call_id, e),
+            }
+        }
+        counter!("maintenance_terminated_calls_total").increment(terminated as u64);
+        info!("Drained {}: {} calls ended", scope, terminated);
+
+        self.registry.publish(MaintenanceEvent {
+            scope: scope.clone(),
+            change: MaintenanceChange::Drained,
+            drain_deadline: self.registry.get(scope).and_then(|s| s.drain_deadline),
+            terminated_calls: terminated,
+            timestamp: Utc::now(),
+        });
+        terminated
+    }
+
+    /// Drain scopes at their deadlines until the registry goes away
+    pub fn spawn(self: Arc<Self>) -> JoinHandle<()> {
+        let mut rx = self.registry.subscribe();
+        tokio::spawn(async move {
+            let mut deadlines: HashMap<MaintenanceScope, JoinHandle<()>> = HashMap::new();
+            loop {
+                let event = match rx.recv().await {
+                    Ok(event) => event,
+                    Err(broadcast::error::RecvError::Lagged(skipped)) => {
+                        warn!("Dropped {} maintenance events (drainer lagging)", skipped);
+                        continue;
+                    }
+                    Err(broadcast::error::RecvError::Closed) => break,
+                };
+                if event.change == MaintenanceChange::Drained {
+                    continue;
+                }
+                if let Some(timer) = deadlines.remove(&event.scope) {
+                    timer.abort();
+                }
+                let Some(deadline) = event.drain_deadline else {
+                    continue;
+                };
+
+                let drainer = self.clone();
+                let scope = event.scope.clone();
+                deadlines.insert(
+                    event.scope,
+                    tokio::spawn(async move {
+                        let wait = (deadline - Utc::now()).to_std().unwrap_or_default();
+                        tokio::time::sleep(wait).await;
+                        // Cleared or rescheduled meanwhile
+                        let current = drainer.registry.get(&scope).and_then(|s| s.drain_deadline);
+                        if current == Some(deadline) {
+                            drainer.drain(&scope).await;
+                        }
+                    }),
+                );
+            }
+            for timer in deadlines.into_values() {
+                timer.abort();
+            }
+        })
+    }
+
+    /// BYE the caller, and the callee when its contact is known
+    ///
+    /// The caller leg is the dialog we answered; the callee leg is
+    /// addressed with the caller's dialog identity the forwarded INVITE
+    /// carried, as its To tag is not tracked.
+    async fn send_byes(&self, call: &CallCheckpoint) {
+        let Some((outbound, local_addr)) = &self.outbound else {
+            return;
+        };
+        let (caller_tag, local_tag) = self
+            .call_router
+            .dialog_tags(&call.call_id)
+            .await
+            .unwrap_or_default();
+
+        let mut byes = Vec::new();
+        if let Some(contact) = call.caller_contact {
+            byes.push((
+                contact,
+                self.build_bye(
+                    &call.call_id,
+                    &call.caller_uri,
+                    contact,
+                    local_addr,
+                    (&call.callee_uri, local_tag.as_deref()),
+                    (&call.caller_uri, caller_tag.as_deref()),
+                ),
+            ));
+        }
+        if let Some(contact) = call.callee_contact {
+            byes.push((
+                contact,
+                self.build_bye(
+                    &call.call_id,
+                    &call.callee_uri,
+                    contact,
+                    local_addr,
+                    (&call.caller_uri, caller_tag.as_deref()),
+                    (&call.callee_uri, None),
+                ),
+            ));
+        }
+
+        for (destination, bye) in byes {
+            debug!("Sending maintenance BYE for call {} to {}", call.call_id, destination);
+            if let Err(e) = outbound
+                .send(OutgoingMessage {
+                    data: Bytes::from(bye),
+                    destination,
+                    protocol: TransportProtocol::Udp,
+                })
+                .await
+            {
+                warn!("Failed to queue BYE for call {}: {}", call.call_id, e);
+            }
+        }
+    }
+
+    fn build_bye(
+        &self,
+        call_id: &str,
+        party_uri: &str,
+        contact: SocketAddr,
+        local_addr: &str,
+        from: (&str, Option<&str>),
+        to: (&str, Option<&str>),
+    ) -> String {
+        let tag = |tag: Option<&str>| tag.map(|t| format!(";tag={}", t)).unwrap_or_default();
+        let user = CallRouter::extract_username(party_uri);
+        format!(
+            "BYE sip:{user}@{contact} SIP/2.0\r\n\
+             Via: SIP/2.0/UDP {local};branch=z9hG4bK{branch}\r\n\
+             Max-Forwards: 70\r\n\
+             From: <{from}>{from_tag}\r\n\
+             To: <{to}>{to_tag}\r\n\
+             Call-ID: {call_id}\r\n\
+             CSeq: {cseq} BYE\r\n\
+             Reason: SIP;cause=503;text=\"Maintenance\"\r\n\
+             Content-Length: 0\r\n\
+             \r\n",
+            user = user,
+            contact = contact,
+            local = local_addr,
+            branch = Uuid::new_v4().simple(),
+            from = from.0,
+            from_tag = tag(from.1),
+            to = to.0,
+            to_tag = tag(to.1),
+            call_id = call_id,
+            cseq = match self.call_router.sequence_vault() {
+                Some(vault) => vault.next_cseq(call_id),
+                None => self.cseq.fetch_add(1, Ordering::Relaxed),
+            },
+        )
+    }
+}
+
+#[async_trait]
+impl Explain for MaintenanceRegistry {
+    fn step(&self) -> &'static str {
+        "maintenance"
+    }
+
+    async fn explain(&self, call: &RoutedCall) -> Option<Explanation> {
+        let state = self.would_refuse(&call.caller_uri, &call.target_uri, call.source)?;
+        let from_trunk = call
+            .source
+            .and_then(|source| self.trunk_of(&source.to_string()))
+            .is_some();
+        let outcome = match (&state.announcement, from_trunk) {
+            (Some(prompt), false) => RouteOutcome::Answered {
+                by: format!("announcement {}", prompt),
+            },
+            _ => RouteOutcome::Rejected {
+                status: 503,
+                reason: format!("{} in maintenance", state.scope),
+            },
+        };
+        Some(Explanation::matched(format!("{} in maintenance", state.scope)).ending(outcome))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::infrastructure::protocols::sip::header_rules::{HeaderRulesConfig, TrunkHeaderRules};
+    use crate::infrastructure::protocols::sip::registrar::Registrar;
+    use crate::infrastructure::protocols::sip::SipMethod;
+    use crate::infrastructure::sequence_vault::{SequenceVault, SequenceVaultConfig};
+    use crate::test_support::{header_value, MockUa, TestServer};
+
+    fn registry_with_trunk() -> MaintenanceRegistry {
+        let mut config = HeaderRulesConfig::default();
+        config.trunks.insert(
+            "carrier".to_string(),
+            TrunkHeaderRules {
+                hosts: vec!["203.0.113.5".to_string()],
+                ..Default::default()
+            },
+        );
+        MaintenanceRegistry::new()
+            .with_header_rules(Arc::new(HeaderRulesEngine::new(config).unwrap()))
+    }
+
+    fn enable() -> MaintenanceRequest {
+        MaintenanceRequest {
+            enabled: true,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_trunk_scope_matches_calls_from_and_to_its_hosts() {
+        let registry = registry_with_trunk();
+        let trunk = MaintenanceScope::Trunk("carrier".to_string());
+        assert_eq!(
+            registry.set(MaintenanceScope::Trunk("other".to_string()), enable()),
+            Err(MaintenanceError::UnknownTrunk("other".to_string()))
+        );
+        registry.set(trunk.clone(), enable()).unwrap();
+
+        let from_trunk = "203.0.113.5".parse().ok();
+        assert!(registry
+            .check_call("sip:+15551234@203.0.113.5", "sip:1001@example.com", from_trunk)
+            .is_some());
+        assert!(registry
+            .check_call("sip:1001@example.com", "sip:+15551234@203.0.113.5", None)
+            .is_some());
+        assert!(registry
+            .check_call("sip:1001@example.com", "sip:1002@example.com", None)
+            .is_none());
+        assert_eq!(registry.get(&trunk).unwrap().rejected_calls, 2);
+
+        // Clearing restores routing at once
+        assert_eq!(
+            registry.set(trunk.clone(), MaintenanceRequest::default()),
+            Ok(None)
+        );
+        assert!(registry
+            .check_call("sip:1001@example.com", "sip:+15551234@203.0.113.5", None)
+            .is_none());
+        assert!(registry.list().is_empty());
+    }
+
+    /// Two tenants, one of them in maintenance with a drain deadline
+    #[tokio::test]
+    async fn test_tenant_maintenance_rejects_new_calls_and_drains_at_deadline() {
+        let server = TestServer::start().await.unwrap();
+        let mut events = server.maintenance().subscribe();
+        let alice = MockUa::bind("sip:alice@acme.test").await.unwrap();
+        let bob = MockUa::bind("sip:bob@acme.test").await.unwrap();
+        let carol = MockUa::bind("sip:carol@globex.test").await.unwrap();
+        let dave = MockUa::bind("sip:dave@globex.test").await.unwrap();
+        for ua in [&bob, &dave] {
+            assert_eq!(ua.register(server.addr()).await.status_code(), 200);
+        }
+
+        // Established before maintenance
+        let responses = alice.invite(server.addr(), "sip:bob@acme.test").await;
+        let established = responses.last().unwrap();
+        assert_eq!(established.status_code(), 200);
+        let established_call = established.call_id().unwrap();
+
+        let acme = MaintenanceScope::Tenant("acme.test".to_string());
+        let deadline = Utc::now() + chrono::Duration::milliseconds(800);
+        server
+            .maintenance()
+            .set(
+                acme.clone(),
+                MaintenanceRequest {
+                    enabled: true,
+                    drain_deadline: Some(deadline),
+                    announcement: None,
+                },
+            )
+            .unwrap();
+        assert_eq!(events.recv().await.unwrap().change, MaintenanceChange::Enabled);
+
+        // New calls involving acme are refused, globex is unaffected
+        let refused = alice.invite(server.addr(), "sip:bob@acme.test").await;
+        assert_eq!(refused.last().unwrap().status_code(), 503);
+        let other = carol.invite(server.addr(), "sip:dave@globex.test").await;
+        assert_eq!(other.last().unwrap().status_code(), 200);
+
+        // Registrations are accepted but flagged
+        let register = bob.register(server.addr()).await;
+        assert_eq!(register.status_code(), 200);
+        assert!(header_value(register.headers(), "Warning").unwrap().contains("maintenance"));
+        let state = server.maintenance().get(&acme).unwrap();
+        assert_eq!((state.rejected_calls, state.flagged_registrations), (1, 1));
+
+        // The established call runs until the deadline, then gets a BYE
+        assert!(server.call_router().get_call_state(&established_call).await.is_some());
+        let bye = alice.next_request(SipMethod::Bye).await;
+        assert_eq!(bye.call_id(), Some(established_call.clone()));
+        let drained = events.recv().await.unwrap();
+        assert_eq!(
+            (drained.change, drained.terminated_calls),
+            (MaintenanceChange::Drained, 1)
+        );
+        assert!(Utc::now() >= deadline);
+        assert!(server.call_router().get_call_state(&established_call).await.is_none());
+        assert_eq!(server.call_router().active_call_count().await, 1);
+
+        // The CDR ends with the maintenance reason
+        let cdr = server.ended_cdr(&established_call).await;
+        assert_eq!(cdr.end_reason.as_deref(), Some("Ended for maintenance"));
+
+        server.stop().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_bye_after_recovery_continues_above_pre_crash_cseqs() {
+        let path = std::env::temp_dir()
+            .join(format!("yakyak-maintenance-{}", Uuid::new_v4()))
+            .join("sequences.json");
+        let call_id = "call-recovered";
+
+        // Before the crash: requests in the dialog, the last ones not
+        // flushed yet
+        let before = SequenceVault::new(100).with_path(&path);
+        let mut sent: Vec<u32> = (0..3).map(|_| before.next_cseq(call_id)).collect();
+        before.flush().await.unwrap();
+        sent.extend((0..10).map(|_| before.next_cseq(call_id)));
+        drop(before);
+
+        // After: the call comes back from its checkpoint and is drained
+        let config = SequenceVaultConfig {
+            state_path: path.to_string_lossy().into_owned(),
+            ..Default::default()
+        };
+        let vault = Arc::new(SequenceVault::open(&config).unwrap());
+        let router = Arc::new(
+            CallRouter::new(Arc::new(Registrar::new())).with_sequence_vault(vault),
+        );
+        router
+            .restore_call(CallCheckpoint {
+                call_id: call_id.to_string(),
+                caller_uri: "sip:alice@acme.test".to_string(),
+                callee_uri: "sip:bob@acme.test".to_string(),
+                caller_contact: "127.0.0.1:5070".parse().ok(),
+                callee_contact: None,
+                established: true,
+                cdr_id: Uuid::new_v4(),
+                local_cseq: 0,
+            })
+            .await
+            .unwrap();
+
+        let registry = Arc::new(MaintenanceRegistry::new());
+        let acme = MaintenanceScope::Tenant("acme.test".to_string());
+        registry.set(acme.clone(), enable()).unwrap();
+        let (tx, mut rx) = mpsc::channel(4);
+        let drainer = MaintenanceDrainer::new(registry, router)
+            .with_outbound(tx, "127.0.0.1:5060".to_string());
+        drainer.drain(&acme).await;
+
+        let bye = rx.recv().await.unwrap();
+        let bye = String::from_utf8_lossy(&bye.data).into_owned();
+        let cseq: u32 = bye
+            .lines()
+            .find_map(|line| line.strip_prefix("CSeq: "))
+            .and_then(|value| value.split_whitespace().next())
+            .and_then(|value| value.parse().ok())
+            .unwrap();
+        assert!(sent.iter().all(|before| cseq > *before), "{} after {:?}", cseq, sent);
+
+        let _ = std::fs::remove_dir_all(path.parent().unwrap());
+    }
+}
@@ -1,6 +1,7 @@
 //! SIP message types and parsing
 
 use bytes::Bytes;
+use rsip::headers::UntypedHeader;
 use rsip::{Header, Headers, Method, Request, Response, Uri};
 use std::fmt;
 use thiserror::Error;
@@ -79,7 +80,14 @@ impl SipMethod {
             Method::Cancel => Some(SipMethod::Cancel),
             Method::Bye => Some(SipMethod::Bye),
             Method::Options => Some(SipMethod::Options),
-            _ => None, // Handle other methods as needed
+            Method::Info => Some(SipMethod::Info),
+            Method::Update => Some(SipMethod::Update),
+            Method::PRack => Some(SipMethod::Prack),
+            Method::Subscribe => Some(SipMethod::Subscribe),
+            Method::Notify => Some(SipMethod::Notify),
+            Method::Refer => Some(SipMethod::Refer),
+            Method::Message => Some(SipMethod::Message),
+            Method::Publish => Some(SipMethod::Publish),
         }
     }
 
@@ -91,7 +99,14 @@ impl SipMethod {
             SipMethod::Cancel => Method::Cancel,
             SipMethod::Bye => Method::Bye,
             SipMethod::Options => Method::Options,
-            _ => Method::Options, // Default fallback
+            SipMethod::Info => Method::Info,
+            SipMethod::Update => Method::Update,
+            SipMethod::Prack => Method::PRack,
+            SipMethod::Subscribe => Method::Subscribe,
+            SipMethod::Notify => Method::Notify,
+            SipMethod::Refer => Method::Refer,
+            SipMethod::Message => Method::Message,
+            SipMethod::Publish => Method::Publish,
         }
     }
 }
@@ -135,27 +150,21 @@ impl SipRequest {
     }
 
     pub fn call_id(&self) -> Option<String> {
-        self.inner
-            .headers
-            .iter()
-            .find_map(|h| match h {
-                Header::CallId(cid) => {
-                    // rsip's CallId .to_string() includes "Call-ID: " prefix
-                    let s = cid.to_string();
-                    s.strip_prefix("Call-ID: ").map(|v| v.to_string()).or(Some(s))
-                }
-                _ => None,
-            })
+        call_id_header(&self.inner.headers)
     }
 
     pub fn from_tag(&self) -> Option<String> {
-        // Simplified - TODO: implement proper tag parsing
-        None
+        self.inner.headers.iter().find_map(|h| match h {
+            Header::From(from) => tag_param(from.value()),
+            _ => None,
+        })
     }
 
     pub fn to_tag(&self) -> Option<String> {
-        // Simplified - TODO: implement proper tag parsing
-        None
+        self.inner.headers.iter().find_map(|h| match h {
+            Header::To(to) => tag_param(to.value()),
+            _ => None,
+        })
     }
 
     pub fn cseq(&self) -> Option<u32> {
@@ -204,11 +213,53 @@ impl SipResponse {
         &self.inner.body
     }
 
+    pub fn call_id(&self) -> Option<String> {
+        call_id_header(&self.inner.headers)
+    }
+
+    pub fn to_tag(&self) -> Option<String> {
+        self.inner.headers.iter().find_map(|h| match h {
+            Header::To(to) => tag_param(to.value()),
+            _ => None,
+        })
+    }
+
+    /// Method of the request answered, from the CSeq
+    pub fn cseq_method(&self) -> Option<SipMethod> {
+        self.inner.headers.iter().find_map(|h| match h {
+            Header::CSeq(cseq) => cseq.method().ok().and_then(|m| SipMethod::from_rsip(&m)),
+            _ => None,
+        })
+    }
+
     pub fn to_bytes(&self) -> Bytes {
         Bytes::from(self.inner.to_string())
     }
 }
 
+/// Call-ID header value
+fn call_id_header(headers: &Headers) -> Option<String> {
+    headers.iter().find_map(|h| match h {
+        Header::CallId(cid) => {
+            // rsip's CallId .to_string() includes "Call-ID: " prefix
+            let s = cid.to_string();
+            s.strip_prefix("Call-ID: ").map(|v| v.to_string()).or(Some(s))
+        }
+        _ => None,
+    })
+}
+
+/// `tag` parameter of a From or To header value
+fn tag_param(value: &str) -> Option<String> {
+    // Parameters inside <...> belong to the URI
+    let params = value.rsplit_once('>').map_or(value, |(_, params)| params);
+    params
+        .split(';')
+        .skip(1)
+        .find_map(|param| param.trim().strip_prefix("tag="))
+        .map(str::to_string)
+}
+
 /// SIP Message (either request or response)
 #[derive(Debug, Clone)]
 pub enum SipMessage {
@@ -255,6 +306,13 @@ impl SipMessage {
         }
     }
 
+    pub fn call_id(&self) -> Option<String> {
+        match self {
+            SipMessage::Request(req) => req.call_id(),
+            SipMessage::Response(resp) => resp.call_id(),
+        }
+    }
+
     pub fn to_bytes(&self) -> Bytes {
         match self {
             SipMessage::Request(req) => req.to_bytes(),
@@ -286,6 +344,8 @@ mod tests {
         assert_eq!(req.method(), Some(SipMethod::Register));
         assert_eq!(req.call_id(), Some("a84b4c76e66710@pc33.example.com".to_string()));
         assert_eq!(req.cseq(), Some(314159));
+        assert_eq!(req.from_tag(), Some("1928301774".to_string()));
+        assert_eq!(req.to_tag(), None);
     }
 
     #[test]
@@ -1,368 +1,718 @@
-/// MESSAGE handler for instant messaging
-use async_trait::async_trait;
-use chrono::Utc;
-use rsip::{Request, Response};
-use std::net::SocketAddr;
-use std::sync::Arc;
-use tokio::sync::RwLock;
-use tracing::{debug, info, warn};
-
+//! MESSAGE handling for instant messaging (RFC 3428)
+//!
+//! Every MESSAGE is stored and forwarded to the recipient's registered
+//! contacts. It counts as delivered once a contact answers the forwarded
+//! MESSAGE with a 2xx; until then it stays pending. Pending messages are
+//! sent again when the recipient registers, retried with backoff while it
+//! stays registered, and marked failed once older than the expiry. The
+//! original sender can get a delivery receipt as a MESSAGE.
+//!
+//! Users are identified by their SIP username.
+
+use super::address::{uri_from_header, uri_socket_addr};
+use super::advertise::AddressAdvertiser;
+use super::builder::ResponseBuilder;
 use super::handler::SipHandler;
-use super::message::SipMessageBuilder;
+use super::hops::{forwarded_max_forwards, DEFAULT_MAX_FORWARDS};
+use super::message::{SipError, SipMethod, SipRequest, SipResponse};
 use super::registrar::Registrar;
-
-/// Message record for history
-#[derive(Debug, Clone)]
-pub struct MessageRecord {
-    pub id: String,
-    pub from: String,
-    pub to: String,
-    pub content_type: String,
-    pub body: String,
-    pub timestamp: chrono::DateTime<Utc>,
-    pub delivered: bool,
+use super::registration_events::{aor_user, RegistrationEventType};
+use super::transport::{OutgoingMessage, TransportProtocol};
+use crate::application::chat::MessageForwarder;
+use crate::domain::instant_messaging::{
+    InstantMessage, MessageContentType, MessageRepository, MessageStatus,
+};
+use async_trait::async_trait;
+use bytes::Bytes;
+use chrono::{DateTime, Duration, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::{Arc, Mutex};
+use tokio::sync::{broadcast, mpsc};
+use tokio::task::JoinHandle;
+use tracing::{debug, info, warn};
+use uuid::Uuid;
+
+/// Content type of IMDN delivery notifications (RFC 5438)
+pub const IMDN_CONTENT_TYPE: &str = "message/imdn+xml";
+
+/// Forwarded MESSAGEs without a response are forgotten after this long
+/// (64*T1, the non-INVITE transaction timeout)
+const IN_FLIGHT_TIMEOUT_SECS: i64 = 32;
+
+/// Delivery receipts sent to the original sender
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ReceiptFormat {
+    /// No receipts
+    #[default]
+    None,
+    /// IMDN delivery notification (`message/imdn+xml`)
+    Imdn,
+    /// `delivered <message id>` with `receipt_content_type`
+    Custom,
 }
 
-/// Message store for offline messages and history
-pub struct MessageStore {
-    messages: Arc<RwLock<Vec<MessageRecord>>>,
+/// Store-and-forward and receipt settings
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct MessagePolicy {
+    /// Delay before the first retry while the recipient is registered;
+    /// doubled after every attempt
+    pub retry_initial_secs: u64,
+    /// Longest delay between retries
+    pub retry_max_secs: u64,
+    /// Undelivered messages older than this are marked failed
+    pub expiry_secs: u64,
+    pub receipts: ReceiptFormat,
+    /// Content type of `Custom` receipts
+    pub receipt_content_type: String,
 }
 
-impl MessageStore {
-    pub fn new() -> Self {
+impl Default for MessagePolicy {
+    fn default() -> Self {
         Self {
-            messages: Arc::new(RwLock::new(Vec::new())),
-        }
-    }
-
-    pub async fn store(&self, message: MessageRecord) {
-        let mut msgs = self.messages.write().await;
-        msgs.push(message);
-    }
-
-    pub async fn get_undelivered(&self, user: &str) -> Vec<MessageRecord> {
-        let msgs = self.messages.read().await;
-        msgs.iter()
-            .filter(|m| m.to == user && !m.delivered)
-            .cloned()
-            .collect()
-    }
-
-    pub async fn mark_delivered(&self, id: &str) {
-        let mut msgs = self.messages.write().await;
-        if let Some(msg) = msgs.iter_mut().find(|m| m.id == id) {
-            msg.delivered = true;
+            retry_initial_secs: 30,
+            retry_max_secs: 900,
+            expiry_secs: 7 * 24 * 3600,
+            receipts: ReceiptFormat::None,
+            receipt_content_type: "application/vnd.yakyak.receipt".to_string(),
         }
     }
+}
 
-    pub async fn count(&self) -> usize {
-        let msgs = self.messages.read().await;
-        msgs.len()
+impl MessagePolicy {
+    /// Delay before retrying a message after its `attempts`-th attempt
+    pub fn retry_delay(&self, attempts: u32) -> Duration {
+        let factor = 1u64 << attempts.saturating_sub(1).min(16);
+        let secs = self
+            .retry_initial_secs
+            .saturating_mul(factor)
+            .min(self.retry_max_secs);
+        Duration::seconds(secs as i64)
     }
-}
 
-impl Default for MessageStore {
-    fn default() -> Self {
-        Self::new()
+    fn is_expired(&self, message: &InstantMessage, now: DateTime<Utc>) -> bool {
+        now - message.timestamp > Duration::seconds(self.expiry_secs as i64)
     }
 }
 
-/// MESSAGE handler for SIP instant messaging
+/// MESSAGE handler with offline store-and-forward
 pub struct MessageHandler {
     registrar: Arc<Registrar>,
-    message_store: Arc<MessageStore>,
+    repository: Arc<dyn MessageRepository>,
+    outbound: mpsc::Sender<OutgoingMessage>,
+    domain: String,
+    /// Address put in Via of forwarded MESSAGEs
+    local_addr: String,
+    /// External address used instead of `local_addr` behind NAT
+    advertiser: Option<Arc<AddressAdvertiser>>,
+    policy: MessagePolicy,
+    /// Forwarded MESSAGEs awaiting a response: Call-ID -> (message, sent at)
+    in_flight: Mutex<HashMap<String, (Uuid, DateTime<Utc>)>>,
+    cseq: AtomicU32,
 }
 
 impl MessageHandler {
-    /// Create a new MESSAGE handler
-    pub fn new(registrar: Arc<Registrar>, message_store: Arc<MessageStore>) -> Self {
+    pub fn new(
+        registrar: Arc<Registrar>,
+        repository: Arc<dyn MessageRepository>,
+        outbound: mpsc::Sender<OutgoingMessage>,
+        domain: String,
+        local_addr: String,
+    ) -> Self {
         Self {
             registrar,
-            message_store,
+            repository,
+            outbound,
+            domain,
+            local_addr,
+            advertiser: None,
+            policy: MessagePolicy::default(),
+            in_flight: Mutex::new(HashMap::new()),
+            cseq: AtomicU32::new(1),
         }
     }
 
-    /// Extract From URI
-    fn extract_from(request: &Request) -> Option<String> {
-        request
-            .from_header()
-            .ok()
-            .and_then(|h| h.uri.to_string().ok())
+    pub fn with_policy(mut self, policy: MessagePolicy) -> Self {
+        self.policy = policy;
+        self
     }
 
-    /// Extract To URI
-    fn extract_to(request: &Request) -> Option<String> {
-        request
-            .to_header()
-            .ok()
-            .and_then(|h| h.uri.to_string().ok())
+    /// Advertise the external address in Via to contacts outside the local
+    /// subnets
+    pub fn with_address_advertiser(mut self, advertiser: Arc<AddressAdvertiser>) -> Self {
+        self.advertiser = Some(advertiser);
+        self
     }
 
-    /// Extract Content-Type header
-    fn extract_content_type(request: &Request) -> String {
-        request
-            .headers
-            .iter()
-            .find(|h| h.name().to_string().to_lowercase() == "content-type")
-            .and_then(|h| h.value().to_string().ok())
-            .unwrap_or_else(|| "text/plain".to_string())
+    /// Deliver pending messages as recipients register
+    pub fn spawn_registration_listener(self: Arc<Self>) -> JoinHandle<()> {
+        let mut rx = self.registrar.subscribe_events();
+        tokio::spawn(async move {
+            loop {
+                match rx.recv().await {
+                    Ok(event) if event.event_type == RegistrationEventType::Added => {
+                        if let Some(user) = aor_user(&event.aor) {
+                            self.recipient_registered(user).await;
+                        }
+                    }
+                    Ok(_) => {}
+                    Err(broadcast::error::RecvError::Lagged(skipped)) => {
+                        warn!("Message delivery missed {} registration events", skipped);
+                    }
+                    Err(broadcast::error::RecvError::Closed) => break,
+                }
+            }
+        })
     }
 
-    /// Extract username from SIP URI
-    fn extract_username(uri: &str) -> String {
-        // Simple extraction: sip:user@domain -> user
-        uri.split('@')
-            .next()
-            .unwrap_or(uri)
-            .trim_start_matches("sip:")
-            .trim_start_matches("sips:")
-            .to_string()
+    /// Mark messages delivered from the responses to forwarded MESSAGEs
+    /// (see `SipServer::subscribe_responses`)
+    pub fn spawn_response_listener(
+        self: Arc<Self>,
+        mut responses: broadcast::Receiver<SipResponse>,
+    ) -> JoinHandle<()> {
+        tokio::spawn(async move {
+            loop {
+                match responses.recv().await {
+                    Ok(response) => self.handle_response(&response).await,
+                    Err(broadcast::error::RecvError::Lagged(skipped)) => {
+                        warn!("Message delivery missed {} responses", skipped);
+                    }
+                    Err(broadcast::error::RecvError::Closed) => break,
+                }
+            }
+        })
     }
-}
 
-#[async_trait]
-impl SipHandler for MessageHandler {
-    async fn handle(&self, request: Request, source: SocketAddr) -> Option<Response> {
-        info!("Handling MESSAGE request from {}", source);
-
-        // Extract From and To
-        let from = Self::extract_from(&request)?;
-        let to = Self::extract_to(&request)?;
-
-        debug!("MESSAGE from {} to {}", from, to);
-
-        // Extract Content-Type
-        let content_type = Self::extract_content_type(&request);
-        debug!("Content-Type: {}", content_type);
-
-        // Extract message body
-        let body = String::from_utf8_lossy(&request.body).to_string();
-        if body.is_empty() {
-            warn!("MESSAGE with empty body");
-            return Some(SipMessageBuilder::create_response(
-                &request,
-                400,
-                "Bad Request - Empty body",
-            ));
+    /// Retry due messages and expire old ones every `interval`
+    pub fn spawn_retry(self: Arc<Self>, interval: std::time::Duration) -> JoinHandle<()> {
+        tokio::spawn(async move {
+            let mut ticker = tokio::time::interval(interval);
+            loop {
+                ticker.tick().await;
+                self.retry_pending().await;
+            }
+        })
+    }
+
+    /// A recipient registered a contact; send everything waiting for it
+    pub async fn recipient_registered(&self, user: &str) {
+        let pending = match self.repository.list_pending_for(user).await {
+            Ok(pending) => pending,
+            Err(e) => {
+                warn!("Failed to list pending messages for {}: {}", user, e);
+                return;
+            }
+        };
+        if !pending.is_empty() {
+            info!("Delivering {} stored messages to {}", pending.len(), user);
+        }
+        let now = Utc::now();
+        for mut message in pending {
+            if self.policy.is_expired(&message, now) {
+                self.expire(&mut message).await;
+            } else {
+                self.deliver(&mut message, DEFAULT_MAX_FORWARDS).await;
+            }
         }
+    }
+
+    /// Retry due messages to registered recipients and expire old ones
+    pub async fn retry_pending(&self) {
+        let pending = match self.repository.list_pending().await {
+            Ok(pending) => pending,
+            Err(e) => {
+                warn!("Failed to list pending messages: {}", e);
+                return;
+            }
+        };
+        let now = Utc::now();
+        for mut message in pending {
+            if self.policy.is_expired(&message, now) {
+                self.expire(&mut message).await;
+            } else if message.is_due(now) && message.attempts > 0 {
+                // Never-attempted messages wait for the recipient to register
+                self.deliver(&mut message, DEFAULT_MAX_FORWARDS).await;
+            }
+        }
+
+        let timeout = Duration::seconds(IN_FLIGHT_TIMEOUT_SECS);
+        self.in_flight
+            .lock()
+            .unwrap()
+            .retain(|_, (_, sent_at)| now - *sent_at < timeout);
+    }
+
+    async fn expire(&self, message: &mut InstantMessage) {
+        info!(
+            "Message {} to {} expired undelivered",
+            message.id, message.to
+        );
+        message.mark_failed();
+        message.next_attempt_at = None;
+        if let Err(e) = self.repository.update(message).await {
+            warn!("Failed to expire message {}: {}", message.id, e);
+        }
+    }
 
-        debug!("Message content: {}", body);
+    /// Forward a pending message to every contact of its recipient
+    ///
+    /// Returns whether it was sent; messages to unregistered recipients
+    /// wait for a registration. Stored messages go out as new requests
+    /// with the default Max-Forwards.
+    async fn deliver(&self, message: &mut InstantMessage, max_forwards: u32) -> bool {
+        let contacts = self.contacts_for(&message.to).await;
+        if contacts.is_empty() {
+            debug!(
+                "Recipient {} is offline, message {} stored",
+                message.to, message.id
+            );
+            return false;
+        }
 
-        // Extract recipient username
-        let to_username = Self::extract_username(&to);
+        for contact in &contacts {
+            let call_id = format!("{}@{}", Uuid::new_v4(), self.domain);
+            let request = self.build_message(OutgoingIm {
+                target: contact,
+                from: &message.from,
+                to: &message.to,
+                call_id: &call_id,
+                content_type: &message.content_type.to_string(),
+                sent_at: Some(message.timestamp),
+                max_forwards,
+                body: &message.content,
+            });
+            self.in_flight
+                .lock()
+                .unwrap()
+                .insert(call_id, (message.id, Utc::now()));
+            self.send(contact, request);
+        }
+
+        message.attempts += 1;
+        message.next_attempt_at = Some(Utc::now() + self.policy.retry_delay(message.attempts));
+        if let Err(e) = self.repository.update(message).await {
+            warn!("Failed to update message {}: {}", message.id, e);
+        }
+        true
+    }
 
-        // Check if recipient is registered (online)
-        let is_online = self.registrar.get_contact(&to_username).await.is_some();
+    /// A contact answered a forwarded MESSAGE
+    pub async fn handle_response(&self, response: &SipResponse) {
+        let status = response.status_code();
+        if status < 200 {
+            return;
+        }
+        let is_message = response_header(response, "CSeq")
+            .is_some_and(|cseq| cseq.trim_end().ends_with("MESSAGE"));
+        let call_id = match response_header(response, "Call-ID") {
+            Some(call_id) if is_message => call_id,
+            _ => return,
+        };
+        let message_id = match self.in_flight.lock().unwrap().remove(&call_id) {
+            Some((message_id, _)) => message_id,
+            None => return,
+        };
+        if status >= 300 {
+            // Retried when due
+            debug!("Message {} refused with {}", message_id, status);
+            return;
+        }
 
-        // Create message record
-        let message_record = MessageRecord {
-            id: uuid::Uuid::new_v4().to_string(),
-            from: from.clone(),
-            to: to.clone(),
-            content_type: content_type.clone(),
-            body: body.clone(),
-            timestamp: Utc::now(),
-            delivered: is_online,
+        let mut message = match self.repository.get_by_id(message_id).await {
+            Ok(Some(message)) if message.status == MessageStatus::Pending => message,
+            // Already delivered through another contact
+            Ok(_) => return,
+            Err(e) => {
+                warn!("Failed to get message {}: {}", message_id, e);
+                return;
+            }
         };
+        message.mark_delivered();
+        message.next_attempt_at = None;
+        if let Err(e) = self.repository.update(&message).await {
+            warn!("Failed to mark message {} delivered: {}", message.id, e);
+            return;
+        }
+        self.in_flight
+            .lock()
+            .unwrap()
+            .retain(|_, (id, _)| *id != message.id);
+        info!("Message {} delivered to {}", message.id, message.to);
+
+        self.send_receipt(&message).await;
+    }
 
-        // Store message
-        self.message_store.store(message_record.clone()).await;
+    /// Tell the original sender its message was delivered
+    async fn send_receipt(&self, message: &InstantMessage) {
+        let (content_type, body) = match self.policy.receipts {
+            ReceiptFormat::None => return,
+            ReceiptFormat::Imdn => (IMDN_CONTENT_TYPE.to_string(), self.imdn_body(message)),
+            ReceiptFormat::Custom => (
+                self.policy.receipt_content_type.clone(),
+                format!("delivered {}", message.id),
+            ),
+        };
 
-        if is_online {
-            info!("Recipient {} is online, delivering message", to_username);
-            // TODO: Forward MESSAGE to recipient using contact from registrar
-            // For now, just mark as delivered
-        } else {
-            info!("Recipient {} is offline, message stored for later delivery", to_username);
+        for contact in self.contacts_for(&message.from).await {
+            let request = self.build_message(OutgoingIm {
+                target: &contact,
+                from: &message.to,
+                to: &message.from,
+                call_id: &format!("{}@{}", Uuid::new_v4(), self.domain),
+                content_type: &content_type,
+                sent_at: None,
+                max_forwards: DEFAULT_MAX_FORWARDS,
+                body: body.as_bytes(),
+            });
+            self.send(&contact, request);
         }
+    }
 
-        // Accept message
-        Some(SipMessageBuilder::create_response(
-            &request,
-            202,
-            "Accepted",
-        ))
+    fn imdn_body(&self, message: &InstantMessage) -> String {
+        let delivered_at = message.delivered_at.unwrap_or_else(Utc::now);
+        format!(
+            "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\r\n\
+             <imdn xmlns=\"urn:ietf:params:xml:ns:imdn\">\r\n\
+             <message-id>{id}</message-id>\r\n\
+             <datetime>{datetime}</datetime>\r\n\
+             <recipient-uri>sip:{to}@{domain}</recipient-uri>\r\n\
+             <original-recipient-uri>sip:{to}@{domain}</original-recipient-uri>\r\n\
+             <delivery-notification><status><delivered/></status></delivery-notification>\r\n\
+             </imdn>\r\n",
+            id = message.id,
+            datetime = delivered_at.to_rfc3339(),
+            to = message.to,
+            domain = self.domain,
+        )
     }
-}
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use rsip::{Method, Uri};
+    async fn contacts_for(&self, user: &str) -> Vec<String> {
+        self.registrar
+            .get_all_registrations()
+            .await
+            .into_iter()
+            .filter(|r| aor_user(&r.aor) == Some(user))
+            .flat_map(|r| r.bindings.into_iter().map(|b| b.contact))
+            .collect()
+    }
 
-    async fn create_test_handler() -> MessageHandler {
-        let registrar = Arc::new(Registrar::new());
-        let message_store = Arc::new(MessageStore::new());
-        MessageHandler::new(registrar, message_store)
+    /// Via sent-by for a MESSAGE to `target`
+    fn sent_by(&self, target: &str) -> String {
+        match &self.advertiser {
+            Some(advertiser) => advertiser
+                .advertise_host_port(&self.local_addr, uri_socket_addr(target).map(|a| a.ip())),
+            None => self.local_addr.clone(),
+        }
     }
 
-    #[tokio::test]
-    async fn test_message_text_plain() {
-        let handler = create_test_handler().await;
-
-        // Create MESSAGE request
-        let mut headers = rsip::Headers::default();
-        headers.push(
-            rsip::Header::From(rsip::headers::From {
-                display_name: Some("Alice".into()),
-                uri: rsip::Uri::try_from("sip:alice@example.com").unwrap(),
-                params: vec![],
-            })
-            .into(),
-        );
-        headers.push(
-            rsip::Header::To(rsip::headers::To {
-                display_name: Some("Bob".into()),
-                uri: rsip::Uri::try_from("sip:bob@example.com").unwrap(),
-                params: vec![],
-            })
-            .into(),
-        );
-        headers.push(
-            rsip::Header::CallId(rsip::headers::CallId {
-                value: "test-msg@example.com".to_string(),
-            })
-            .into(),
-        );
-        headers.push(
-            rsip::Header::Other("Content-Type".into(), "text/plain".as_bytes().to_vec()).into(),
-        );
+    fn build_message(&self, im: OutgoingIm<'_>) -> Vec<u8> {
+        // Stored messages carry the time they were sent
+        let date = im
+            .sent_at
+            .map(|at| format!("Date: {}\r\n", at.format("%a, %d %b %Y %H:%M:%S GMT")))
+            .unwrap_or_default();
+        let mut request = format!(
+            "MESSAGE {target} SIP/2.0\r\n\
+             Via: SIP/2.0/UDP {local};branch=z9hG4bK{branch}\r\n\
+             Max-Forwards: {max_forwards}\r\n\
+             From: <sip:{from}@{domain}>;tag={from_tag}\r\n\
+             To: <sip:{to}@{domain}>\r\n\
+             Call-ID: {call_id}\r\n\
+             CSeq: {cseq} MESSAGE\r\n\
+             {date}\
+             Content-Type: {content_type}\r\n\
+             Content-Length: {len}\r\n\
+             \r\n",
+            target = im.target,
+            local = self.sent_by(im.target),
+            branch = Uuid::new_v4().simple(),
+            max_forwards = im.max_forwards,
+            from = im.from,
+            domain = self.domain,
+            from_tag = Uuid::new_v4().simple(),
+            to = im.to,
+            call_id = im.call_id,
+            cseq = self.cseq.fetch_add(1, Ordering::Relaxed),
+            date = date,
+            content_type = im.content_type,
+            len = im.body.len(),
+        )
+        .into_bytes();
+        request.extend_from_slice(im.body);
+        request
+    }
 
-        let body = b"Hello, Bob!".to_vec();
-
-        let request = Request {
-            method: Method::Message,
-            uri: Uri {
-                scheme: Some(rsip::Scheme::Sip),
-                auth: None,
-                host_with_port: rsip::HostWithPort {
-                    host: rsip::Host::Domain("example.com".into()),
-                    port: None,
-                },
-                params: vec![],
-                headers: vec![],
-            },
-            version: rsip::Version::V2,
-            headers,
-            body,
+    fn send(&self, contact: &str, request: Vec<u8>) {
+        let destination = match uri_socket_addr(contact) {
+            Some(destination) => destination,
+            None => {
+                warn!("Cannot send MESSAGE to unresolvable contact {}", contact);
+                return;
+            }
+        };
+        let protocol = if contact.to_lowercase().contains("transport=tcp") {
+            TransportProtocol::Tcp
+        } else {
+            TransportProtocol::Udp
         };
 
-        let source: SocketAddr = "127.0.0.1:5060".parse().unwrap();
-        let response = handler.handle(request, source).await.unwrap();
-
-        assert_eq!(response.status_code.into_inner(), 202);
+        debug!("Sending MESSAGE to {} ({})", contact, destination);
+        if let Err(e) = self.outbound.try_send(OutgoingMessage {
+            data: Bytes::from(request),
+            destination,
+            protocol,
+        }) {
+            warn!("Failed to queue MESSAGE to {}: {}", contact, e);
+        }
+    }
+}
 
-        // Check message was stored
-        assert_eq!(handler.message_store.count().await, 1);
+/// Chat messages to users without a WebSocket session go to their devices
+#[async_trait]
+impl MessageForwarder for MessageHandler {
+    async fn forward(&self, message: &mut InstantMessage) -> bool {
+        self.deliver(message, DEFAULT_MAX_FORWARDS).await
     }
+}
 
-    #[tokio::test]
-    async fn test_message_empty_body() {
-        let handler = create_test_handler().await;
-
-        // Create MESSAGE request with empty body
-        let mut headers = rsip::Headers::default();
-        headers.push(
-            rsip::Header::From(rsip::headers::From {
-                display_name: Some("Alice".into()),
-                uri: rsip::Uri::try_from("sip:alice@example.com").unwrap(),
-                params: vec![],
-            })
-            .into(),
-        );
-        headers.push(
-            rsip::Header::To(rsip::headers::To {
-                display_name: Some("Bob".into()),
-                uri: rsip::Uri::try_from("sip:bob@example.com").unwrap(),
-                params: vec![],
-            })
-            .into(),
+#[async_trait]
+impl SipHandler for MessageHandler {
+    async fn handle_request(&self, request: SipRequest) -> Result<SipResponse, SipError> {
+        let from = header_value(&request, "From").map(|from| uri_from_header(&from).to_string());
+        let to = header_value(&request, "To").map(|to| uri_from_header(&to).to_string());
+        let (sender, recipient) = match (
+            from.as_deref().and_then(aor_user),
+            to.as_deref().and_then(aor_user),
+        ) {
+            (Some(sender), Some(recipient)) => (sender.to_string(), recipient.to_string()),
+            _ => return ResponseBuilder::new(400).build_for_request(&request),
+        };
+
+        let max_forwards = match forwarded_max_forwards(&request) {
+            Some(max_forwards) => max_forwards,
+            None => {
+                warn!("MESSAGE from {} to {}: too many hops", sender, recipient);
+                return ResponseBuilder::new(483).build_for_request(&request);
+            }
+        };
+        if request.body().is_empty() {
+            warn!("MESSAGE from {} with empty body", sender);
+            return ResponseBuilder::new(400).build_for_request(&request);
+        }
+        let content_type =
+            header_value(&request, "Content-Type").unwrap_or_else(|| "text/plain".to_string());
+
+        let mut message = InstantMessage::new(
+            sender,
+            recipient,
+            request.body().to_vec(),
+            MessageContentType::from_str(&content_type),
         );
-        headers.push(
-            rsip::Header::CallId(rsip::headers::CallId {
-                value: "test-msg@example.com".to_string(),
-            })
-            .into(),
+        if let Err(e) = self.repository.save(&message).await {
+            warn!("Failed to store message from {}: {}", message.from, e);
+            return ResponseBuilder::server_internal_error().build_for_request(&request);
+        }
+        debug!(
+            "MESSAGE {} from {} to {}",
+            message.id, message.from, message.to
         );
 
-        let request = Request {
-            method: Method::Message,
-            uri: Uri {
-                scheme: Some(rsip::Scheme::Sip),
-                auth: None,
-                host_with_port: rsip::HostWithPort {
-                    host: rsip::Host::Domain("example.com".into()),
-                    port: None,
-                },
-                params: vec![],
-                headers: vec![],
-            },
-            version: rsip::Version::V2,
-            headers,
-            body: vec![],
-        };
+        self.deliver(&mut message, max_forwards).await;
 
-        let source: SocketAddr = "127.0.0.1:5060".parse().unwrap();
-        let response = handler.handle(request, source).await.unwrap();
+        ResponseBuilder::new(202)
+            .to_tag(&Uuid::new_v4().simple().to_string())
+            .build_for_request(&request)
+    }
 
-        assert_eq!(response.status_code.into_inner(), 400);
+    fn can_handle(&self, method: SipMethod) -> bool {
+        method == SipMethod::Message
     }
+}
 
-    #[tokio::test]
-    async fn test_message_store_offline() {
-        let handler = create_test_handler().await;
-
-        // Create MESSAGE request to offline user
-        let mut headers = rsip::Headers::default();
-        headers.push(
-            rsip::Header::From(rsip::headers::From {
-                display_name: Some("Alice".into()),
-                uri: rsip::Uri::try_from("sip:alice@example.com").unwrap(),
-                params: vec![],
-            })
-            .into(),
-        );
-        headers.push(
-            rsip::Header::To(rsip::headers::To {
-                display_name: Some("Bob".into()),
-                uri: rsip::Uri::try_from("sip:bob@example.com").unwrap(),
-                params: vec![],
-            })
-            .into(),
-        );
-        headers.push(
-            rsip::Header::CallId(rsip::headers::CallId {
-                value: "test-msg@example.com".to_string(),
-            })
-            .into(),
+/// A MESSAGE originated by the server
+struct OutgoingIm<'a> {
+    target: &'a str,
+    from: &'a str,
+    to: &'a str,
+    call_id: &'a str,
+    content_type: &'a str,
+    /// Original send time of a stored message
+    sent_at: Option<DateTime<Utc>>,
+    max_forwards: u32,
+    body: &'a [u8],
+}
+
+fn header_value(request: &SipRequest, name: &str) -> Option<String> {
+    request.headers().iter().find_map(|h| {
+        let line = h.to_string();
+        let (header, value) = line.split_once(':')?;
+        header
+            .trim()
+            .eq_ignore_ascii_case(name)
+            .then(|| value.trim().to_string())
+    })
+}
+
+fn response_header(response: &SipResponse, name: &str) -> Option<String> {
+    response.headers().iter().find_map(|h| {
+        let line = h.to_string();
+        let (header, value) = line.split_once(':')?;
+        header
+            .trim()
+            .eq_ignore_ascii_case(name)
+            .then(|| value.trim().to_string())
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::infrastructure::persistence::MemoryMessageRepository;
+
+    fn setup(
+        policy: MessagePolicy,
+    ) -> (
+        Arc<Registrar>,
+        Arc<MemoryMessageRepository>,
+        MessageHandler,
+        mpsc::Receiver<OutgoingMessage>,
+    ) {
+        let registrar = Arc::new(Registrar::new());
+        let repository = Arc::new(MemoryMessageRepository::new());
+        let (tx, rx) = mpsc::channel(16);
+        let handler = MessageHandler::new(
+            registrar.clone(),
+            repository.clone(),
+            tx,
+            "example.com".to_string(),
+            "192.0.2.1:5060".to_string(),
+        )
+        .with_policy(policy);
+        (registrar, repository, handler, rx)
+    }
+
+    fn message_request(from: &str, to: &str, body: &str) -> SipRequest {
+        let data = format!(
+            "MESSAGE sip:{to}@example.com SIP/2.0\r\n\
+             Via: SIP/2.0/UDP 10.0.0.5:5060;branch=z9hG4bKim1\r\n\
+             From: <sip:{from}@example.com>;tag=im1\r\n\
+             To: <sip:{to}@example.com>\r\n\
+             Call-ID: im-1@10.0.0.5\r\n\
+             CSeq: 1 MESSAGE\r\n\
+             Content-Type: text/plain\r\n\
+             Content-Length: {len}\r\n\
+             \r\n\
+             {body}",
+            from = from,
+            to = to,
+            len = body.len(),
+            body = body,
         );
+        SipRequest::parse(data.as_bytes()).unwrap()
+    }
 
-        let body = b"Hello, Bob!".to_vec();
-
-        let request = Request {
-            method: Method::Message,
-            uri: Uri {
-                scheme: Some(rsip::Scheme::Sip),
-                auth: None,
-                host_with_port: rsip::HostWithPort {
-                    host: rsip::Host::Domain("example.com".into()),
-                    port: None,
-                },
-                params: vec![],
-                headers: vec![],
-            },
-            version: rsip::Version::V2,
-            headers,
-            body,
-        };
+    fn ok_for(forwarded: &OutgoingMessage) -> SipResponse {
+        let request = SipRequest::parse(&forwarded.data).unwrap();
+        let data = format!(
+            "SIP/2.0 200 OK\r\n\
+             Via: SIP/2.0/UDP 192.0.2.1:5060;branch=z9hG4bKfwd\r\n\
+             From: <sip:alice@example.com>;tag=a\r\n\
+             To: <sip:bob@example.com>;tag=b\r\n\
+             Call-ID: {}\r\n\
+             CSeq: {} MESSAGE\r\n\
+             Content-Length: 0\r\n\r\n",
+            request.call_id().unwrap(),
+            request.cseq().unwrap(),
+        );
+        SipResponse::parse(data.as_bytes()).unwrap()
+    }
 
-        let source: SocketAddr = "127.0.0.1:5060".parse().unwrap();
-        let response = handler.handle(request, source).await.unwrap();
+    #[tokio::test]
+    async fn test_offline_message_delivered_on_registration() {
+        let (registrar, repository, handler, mut rx) = setup(MessagePolicy {
+            receipts: ReceiptFormat::Custom,
+            ..MessagePolicy::default()
+        });
+
+        // Bob is offline: stored, nothing forwarded
+        let response = handler
+            .handle_request(message_request("alice", "bob", "Call me"))
+            .await
+            .unwrap();
+        assert_eq!(response.status_code(), 202);
+        let pending = repository.list_pending_for("bob").await.unwrap();
+        assert_eq!(pending.len(), 1);
+        assert_eq!(pending[0].from, "alice");
+        assert!(rx.try_recv().is_err());
+
+        let empty = handler
+            .handle_request(message_request("alice", "bob", ""))
+            .await
+            .unwrap();
+        assert_eq!(empty.status_code(), 400);
+
+        // Bob registers: the stored message is forwarded
+        registrar
+            .add_binding(
+                "sip:bob@example.com".to_string(),
+                "sip:bob@10.0.0.7:5062".to_string(),
+                3600,
+            )
+            .await
+            .unwrap();
+        registrar
+            .add_binding(
+                "sip:alice@example.com".to_string(),
+                "sip:alice@10.0.0.5:5060".to_string(),
+                3600,
+            )
+            .await
+            .unwrap();
+        handler.recipient_registered("bob").await;
+
+        let forwarded = rx.try_recv().unwrap();
+        assert_eq!(forwarded.destination, "10.0.0.7:5062".parse().unwrap());
+        let request = SipRequest::parse(&forwarded.data).unwrap();
+        assert_eq!(request.method(), Some(SipMethod::Message));
+        assert_eq!(request.body(), b"Call me");
+        let stored = repository.get_by_id(pending[0].id).await.unwrap().unwrap();
+        assert_eq!(stored.status, MessageStatus::Pending);
+        assert_eq!(stored.attempts, 1);
+
+        // Delivered on the device's 2xx; Alice gets a receipt
+        handler.handle_response(&ok_for(&forwarded)).await;
+        let stored = repository.get_by_id(pending[0].id).await.unwrap().unwrap();
+        assert_eq!(stored.status, MessageStatus::Delivered);
+        assert!(stored.delivered_at.is_some());
+
+        let receipt = rx.try_recv().unwrap();
+        assert_eq!(receipt.destination, "10.0.0.5:5060".parse().unwrap());
+        let receipt = SipRequest::parse(&receipt.data).unwrap();
+        assert_eq!(
+            String::from_utf8_lossy(receipt.body()),
+            format!("delivered {}", stored.id)
+        );
 
-        assert_eq!(response.status_code.into_inner(), 202);
+        // A retransmitted 200 changes nothing
+        handler.handle_response(&ok_for(&forwarded)).await;
+        assert!(rx.try_recv().is_err());
+    }
 
-        // Check undelivered messages for bob
-        let undelivered = handler.message_store.get_undelivered("sip:bob@example.com").await;
-        assert_eq!(undelivered.len(), 1);
+    #[test]
+    fn test_retry_backoff() {
+        let policy = MessagePolicy {
+            retry_initial_secs: 30,
+            retry_max_secs: 200,
+            ..MessagePolicy::default()
+        };
+        assert_eq!(policy.retry_delay(1), Duration::seconds(30));
+        assert_eq!(policy.retry_delay(2), Duration::seconds(60));
+        assert_eq!(policy.retry_delay(3), Duration::seconds(120));
+        assert_eq!(policy.retry_delay(4), Duration::seconds(200));
+        assert_eq!(policy.retry_delay(40), Duration::seconds(200));
     }
 }
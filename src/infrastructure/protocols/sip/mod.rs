@@ -25,43 +25,139 @@
 //! └─────────────────────────┘
 //! ```
 
+pub mod address;
+pub mod advertise;
 pub mod auth;
 #[cfg(feature = "postgres")]
 pub mod auth_db;
 pub mod auth_enhanced;
+pub mod branding;
 pub mod builder;
 pub mod call_handler;
 pub mod call_router;
 pub mod call_state;
+pub mod codec_fallback;
+pub mod campaign_dialer;
+pub mod connection;
+pub mod device_resync;
 pub mod dialog;
 pub mod handler;
+pub mod header_rules;
 pub mod hold_manager;
+pub mod hold_supervisor;
+pub mod hops;
+pub mod info_handler;
+pub mod internal_services;
+pub mod listener;
+pub mod maintenance;
 pub mod message;
+pub mod message_handler;
+pub mod mwi_notifier;
+pub mod options_handler;
+pub mod outbound_registration;
+pub mod overload;
+pub mod quirks;
 // Temporarily disabled - under development
-// pub mod message_handler;
 // pub mod notify_handler;
 // pub mod refer_handler;
 pub mod registrar;
+pub mod registration_events;
+pub mod redirect;
+pub mod replaces;
+pub mod resolver;
 pub mod rport;
+pub mod screening;
 pub mod sdp;
 pub mod server;
+pub mod srtp_rekey;
+pub mod survey;
+pub mod survivability;
 // pub mod subscribe_handler;
 pub mod transaction;
+pub mod transfer;
 pub mod transport;
+pub mod trunk_manager;
+pub mod voicemail_callback;
+pub mod warm_transfer;
 
+pub use advertise::{AddressAdvertiser, ExternalAddressConfig, Subnet};
 pub use auth::{AuthChallenge, DigestAuth, SipAuthenticator, UserCredentials};
 #[cfg(feature = "postgres")]
-pub use auth_db::DigestAuthDb;
+pub use auth_db::{DigestAuthDb, Ha1Cache};
+pub use branding::brand_request;
 pub use call_handler::{AckHandler, ByeHandler, CallSession, CancelHandler, InviteHandler};
-pub use call_router::{ActiveCallInfo, BridgedCall, CallLegInfo, CallRouter};
+pub use call_router::{
+    ActiveCallInfo, BridgedCall, CallCheckpoint, CallLegInfo, CallRouter, HeldCall,
+    LegMedia, ScreeningOutcome, Takeover, TakeoverRequest,
+};
+pub use campaign_dialer::SipCampaignDialer;
 pub use call_state::{CallDirection, CallEvent, CallLeg, CallState, CallStateMachine, CallStats};
+pub use connection::{ConnectionConfig, ConnectionTable};
+pub use device_resync::{DeviceResyncError, DeviceResyncer, ResyncOutcome};
+pub use dialog::{DialogManager, DialogMedia, LocalReinviteOutcome, ReinviteAction, ReinviteSender};
+pub use hold_supervisor::{
+    HoldLimits, HoldPolicy, HoldRecovery, HoldSupervisionEvent, HoldSupervisor, HoldTimers,
+};
+pub use header_rules::{
+    HeaderContext, HeaderDirection, HeaderRule, HeaderRuleError, HeaderRules, HeaderRulesConfig,
+    HeaderRulesEngine, RouteHeaderRules, TrunkHeaderRules,
+};
+pub use hops::HopTracker;
+pub use info_handler::{DtmfSender, InfoHandler, InfoPolicy, InfoSender};
+pub use internal_services::{
+    InternalService, InternalServiceError, InternalServiceHandler, InternalServicesConfig,
+    ServiceLimits,
+};
+pub use listener::{ListenerInfo, ListenerOutcome, ListenerReport, ListenerSpec, ListenerState};
+pub use maintenance::{
+    MaintenanceChange, MaintenanceDrainer, MaintenanceError, MaintenanceEvent, MaintenanceRegistry,
+    MaintenanceRequest, MaintenanceScope, ScopeMaintenance,
+};
 pub use message::{SipMessage, SipMethod, SipRequest, SipResponse};
+pub use message_handler::{MessageHandler, MessagePolicy, ReceiptFormat};
+pub use mwi_notifier::{MwiNotifier, MwiVoicemailRepository};
+pub use options_handler::{allow_header, OptionsHandler};
+pub use outbound_registration::{
+    OutboundRegistration, OutboundRegistrationPolicy, RegistrationState, TrunkRegistrationStatus,
+};
+pub use overload::{OverloadConfig, OverloadLevel, OverloadMonitor, OverloadSample, OverloadStatus};
+pub use quirks::{Quirk, QuirkRule, QuirksRegistry};
+pub use redirect::{InviteForwarder, RedirectFollower, RedirectOutcome, RedirectPolicy, TrustZone};
 pub use registrar::Registrar;
-pub use sdp::SdpSession;
+pub use replaces::{LegReleaser, ReplacedLeg, Replaces, TakeoverError, TakeoverPolicy};
+pub use resolver::{
+    DnsLookup, FailoverForwarder, HickoryLookup, SipResolver, SipResolverConfig, SipTarget,
+};
+pub use registration_events::{
+    ChurnCause, ChurnConfig, RegistrationEvent, RegistrationEventLog, RegistrationEventType,
+};
+pub use codec_fallback::{CodecFallback, CodecFallbackEvent, CodecFallbackPolicy};
+pub use screening::CallScreener;
+pub use sdp::{SdpOriginState, SdpSession};
 pub use server::{SipServer, SipServerConfig};
+pub use srtp_rekey::{SrtpRekeyEvent, SrtpRekeyPolicy, SrtpRekeyer};
+pub use survey::SurveyRunner;
+pub use survivability::{
+    DeferredItem, DeferredKind, SurvivabilityChange, SurvivabilityConfig, SurvivabilityEvent,
+    SurvivabilityManager, SurvivabilityStatus, SurvivableEmailSender, TrunkRegistrationMonitor,
+    UpstreamMonitor, UpstreamSync, VoicemailEmailSync,
+};
 pub use transaction::{
-    InviteClientState, InviteServerState, NonInviteClientState, NonInviteServerState,
-    SipTimers, TimerType, Transaction, TransactionId, TransactionLayer, TransactionState,
-    TransactionTimerAction,
+    EffectiveSipTimers, InviteClientState, InviteServerState, NonInviteClientState,
+    NonInviteServerState, SipTimerConfig, SipTimerOverrides, SipTimers, TimerType, Transaction,
+    TransactionId, TransactionLayer, TransactionState, TransactionTimerAction,
+};
+pub use transfer::{
+    PendingTransfer, ReferNotify, ReferSubscription, SubscriptionEndReason, TransferNotifier,
+    TransferOutcome, TransferPolicy,
 };
 pub use transport::{Transport, TransportProtocol};
+pub use trunk_manager::{
+    ChannelDirection, TrunkChannelConfig, TrunkChannelLimits, TrunkFull, TrunkManager,
+    TrunkOccupancy,
+};
+pub use voicemail_callback::SipVoicemailCallback;
+pub use warm_transfer::{
+    TransferAction, TransferPhase, TransferSession, WarmTransferConfig, WarmTransferError,
+    WarmTransferManager,
+};
@@ -0,0 +1,750 @@
+//! Message waiting indicator delivery
+//!
+//! Sends `message-summary` NOTIFYs (RFC 3842) so phones light their lamp:
+//! unsolicited NOTIFYs to every registered contact of the mailbox owner, and
+//! in-dialog NOTIFYs to phones that SUBSCRIBE to the package. A contact gets
+//! a NOTIFY when it registers while the mailbox has unread messages, and all
+//! contacts get one whenever the mailbox changes (deposit, delete,
+//! mark-as-read).
+//!
+//! Mailbox IDs are the owner's SIP username.
+
+use super::address::uri_socket_addr;
+use super::advertise::AddressAdvertiser;
+use super::builder::ResponseBuilder;
+use super::handler::SipHandler;
+use super::message::{SipError, SipMethod, SipRequest, SipResponse};
+use super::registrar::Registrar;
+use super::registration_events::{aor_user, RegistrationEventType};
+use super::transport::{OutgoingMessage, TransportProtocol};
+use crate::domain::mwi::{MessageSummary, MwiAccount, MwiManager, MwiSubscription};
+use crate::domain::voicemail::{VoicemailMailbox, VoicemailMessage, VoicemailRepository, VoicemailStatus};
+use async_trait::async_trait;
+use bytes::Bytes;
+use rsip::Header;
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::{Arc, Mutex};
+use tokio::sync::{broadcast, mpsc, OwnedMutexGuard};
+use tokio::task::JoinHandle;
+use tracing::{debug, info, warn};
+use uuid::Uuid;
+
+/// Event package handled by the notifier
+pub const MESSAGE_SUMMARY_EVENT: &str = "message-summary";
+
+/// Default subscription duration when SUBSCRIBE has no Expires
+const DEFAULT_SUBSCRIPTION_EXPIRES: u32 = 3600;
+
+/// Sends MWI NOTIFYs for voicemail mailboxes
+pub struct MwiNotifier {
+    registrar: Arc<Registrar>,
+    voicemail: Arc<dyn VoicemailRepository>,
+    subscriptions: Arc<MwiManager>,
+    outbound: mpsc::Sender<OutgoingMessage>,
+    domain: String,
+    /// Address put in Via/Contact of our NOTIFYs
+    local_addr: String,
+    /// External address used instead of `local_addr` behind NAT
+    advertiser: Option<Arc<AddressAdvertiser>>,
+    /// Serializes refreshes per mailbox: a REGISTER racing a deposit always
+    /// ends with every contact holding the latest counts
+    mailbox_locks: Mutex<HashMap<String, Arc<tokio::sync::Mutex<()>>>>,
+    /// SUBSCRIBE dialogs by Call-ID
+    dialogs: Mutex<HashMap<String, Uuid>>,
+    cseq: AtomicU32,
+}
+
+impl MwiNotifier {
+    pub fn new(
+        registrar: Arc<Registrar>,
+        voicemail: Arc<dyn VoicemailRepository>,
+        outbound: mpsc::Sender<OutgoingMessage>,
+        domain: String,
+        local_addr: String,
+    ) -> Self {
+        Self {
+            registrar,
+            voicemail,
+            subscriptions: Arc::new(MwiManager::new()),
+            outbound,
+            domain,
+            local_addr,
+            advertiser: None,
+            mailbox_locks: Mutex::new(HashMap::new()),
+            dialogs: Mutex::new(HashMap::new()),
+            cseq: AtomicU32::new(1),
+        }
+    }
+
+    /// Advertise the external address in Via/Contact to contacts outside
+    /// the local subnets
+    pub fn with_address_advertiser(mut self, advertiser: Arc<AddressAdvertiser>) -> Self {
+        self.advertiser = Some(advertiser);
+        self
+    }
+
+    /// Via sent-by and Contact host:port for a NOTIFY to `target`
+    fn sent_by(&self, target: &str) -> String {
+        match &self.advertiser {
+            Some(advertiser) => advertiser
+                .advertise_host_port(&self.local_addr, uri_socket_addr(target).map(|a| a.ip())),
+            None => self.local_addr.clone(),
+        }
+    }
+
+    /// Subscription state (for statistics)
+    pub fn subscriptions(&self) -> Arc<MwiManager> {
+        self.subscriptions.clone()
+    }
+
+    /// Send NOTIFYs to contacts as they register
+    pub fn spawn_registration_listener(self: Arc<Self>) -> JoinHandle<()> {
+        let mut rx = self.registrar.subscribe_events();
+        tokio::spawn(async move {
+            loop {
+                match rx.recv().await {
+                    Ok(event) => {
+                        if matches!(
+                            event.event_type,
+                            RegistrationEventType::Added | RegistrationEventType::Refreshed
+                        ) {
+                            if let Some(contact) = &event.contact {
+                                self.contact_registered(&event.aor, contact).await;
+                            }
+                        }
+                    }
+                    Err(broadcast::error::RecvError::Lagged(skipped)) => {
+                        warn!("MWI missed {} registration events", skipped);
+                    }
+                    Err(broadcast::error::RecvError::Closed) => break,
+                }
+            }
+        })
+    }
+
+    async fn lock_mailbox(&self, mailbox_id: &str) -> OwnedMutexGuard<()> {
+        let lock = self
+            .mailbox_locks
+            .lock()
+            .unwrap()
+            .entry(mailbox_id.to_string())
+            .or_default()
+            .clone();
+        lock.lock_owned().await
+    }
+
+    async fn summary(&self, mailbox_id: &str) -> Option<MessageSummary> {
+        let account = MwiAccount::from_mailbox(mailbox_id, &self.domain);
+        match MessageSummary::from_voicemail(self.voicemail.as_ref(), account, mailbox_id).await {
+            Ok(summary) => Some(summary),
+            Err(e) => {
+                warn!("Failed to count messages for mailbox {}: {}", mailbox_id, e);
+                None
+            }
+        }
+    }
+
+    async fn contacts_for(&self, mailbox_id: &str) -> Vec<String> {
+        self.registrar
+            .get_all_registrations()
+            .await
+            .into_iter()
+            .filter(|r| aor_user(&r.aor) == Some(mailbox_id))
+            .flat_map(|r| r.bindings.into_iter().map(|b| b.contact))
+            .collect()
+    }
+
+    /// A contact registered; light its lamp if there are unread messages
+    pub async fn contact_registered(&self, aor: &str, contact: &str) {
+        let mailbox_id = match aor_user(aor) {
+            Some(user) => user.to_string(),
+            None => return,
+        };
+
+        // Counts are read under the mailbox lock, after the binding is stored
+        let _guard = self.lock_mailbox(&mailbox_id).await;
+        if let Some(summary) = self.summary(&mailbox_id).await {
+            if summary.has_new_messages() {
+                self.send_unsolicited(contact, &summary);
+            }
+        }
+    }
+
+    /// The mailbox changed; send the new counts to every contact and subscriber
+    pub async fn mailbox_changed(&self, mailbox_id: &str) {
+        let _guard = self.lock_mailbox(mailbox_id).await;
+        let summary = match self.summary(mailbox_id).await {
+            Some(summary) => summary,
+            None => return,
+        };
+        self.subscriptions.update_summary(summary.clone());
+
+        let subscribed = self.subscriptions.list_subscriptions(&summary.account);
+        for contact in self.contacts_for(mailbox_id).await {
+            // Subscribed phones get the in-dialog NOTIFY instead
+            if !subscribed.iter().any(|s| s.contact == contact) {
+                self.send_unsolicited(&contact, &summary);
+            }
+        }
+        for subscription in &subscribed {
+            self.send_in_dialog(subscription, &summary);
+        }
+
+        info!(
+            "MWI for {}: {} new, {} old",
+            mailbox_id,
+            summary.total_new(),
+            summary.total_old()
+        );
+    }
+
+    fn next_cseq(&self) -> u32 {
+        self.cseq.fetch_add(1, Ordering::Relaxed)
+    }
+
+    fn send_unsolicited(&self, contact: &str, summary: &MessageSummary) {
+        let request = self.build_notify(NotifyDialog {
+            target: contact,
+            from: &summary.account.uri,
+            from_tag: &Uuid::new_v4().simple().to_string(),
+            to: &summary.account.uri,
+            to_tag: None,
+            call_id: &format!("{}@{}", Uuid::new_v4(), self.domain),
+            subscription_state: "active",
+        }, summary);
+        self.send(contact, request);
+    }
+
+    fn send_in_dialog(&self, subscription: &MwiSubscription, summary: &MessageSummary) {
+        let state = if subscription.is_active() {
+            format!("active;expires={}", subscription.time_until_expiry().max(0))
+        } else {
+            "terminated;reason=timeout".to_string()
+        };
+        let request = self.build_notify(NotifyDialog {
+            target: &subscription.contact,
+            from: &subscription.account.uri,
+            from_tag: &subscription.to_tag,
+            to: &subscription.subscriber,
+            to_tag: Some(&subscription.from_tag),
+            call_id: &subscription.call_id,
+            subscription_state: &state,
+        }, summary);
+        self.send(&subscription.contact, request);
+    }
+
+    fn build_notify(&self, dialog: NotifyDialog<'_>, summary: &MessageSummary) -> String {
+        let body = summary.to_message_summary_body();
+        let to_tag = dialog
+            .to_tag
+            .map(|tag| format!(";tag={}", tag))
+            .unwrap_or_default();
+        format!(
+            "NOTIFY {target} SIP/2.0\r\n\
+             Via: SIP/2.0/UDP {local};branch=z9hG4bK{branch}\r\n\
+             Max-Forwards: 70\r\n\
+             From: <{from}>;tag={from_tag}\r\n\
+             To: <{to}>{to_tag}\r\n\
+             Call-ID: {call_id}\r\n\
+             CSeq: {cseq} NOTIFY\r\n\
+             Contact: <sip:{local}>\r\n\
+             Event: {event}\r\n\
+             Subscription-State: {state}\r\n\
+             Content-Type: application/simple-message-summary\r\n\
+             Content-Length: {len}\r\n\
+             \r\n\
+             {body}",
+            target = dialog.target,
+            local = self.sent_by(dialog.target),
+            branch = Uuid::new_v4().simple(),
+            from = dialog.from,
+            from_tag = dialog.from_tag,
+            to = dialog.to,
+            to_tag = to_tag,
+            call_id = dialog.call_id,
+            cseq = self.next_cseq(),
+            event = MESSAGE_SUMMARY_EVENT,
+            state = dialog.subscription_state,
+            len = body.len(),
+            body = body,
+        )
+    }
+
+    fn send(&self, contact: &str, request: String) {
+        let destination = match uri_socket_addr(contact) {
+            Some(destination) => destination,
+            None => {
+                warn!("Cannot send MWI NOTIFY to unresolvable contact {}", contact);
+                return;
+            }
+        };
+        let protocol = if contact.to_lowercase().contains("transport=tcp") {
+            TransportProtocol::Tcp
+        } else {
+            TransportProtocol::Udp
+        };
+
+        debug!("Sending MWI NOTIFY to {} ({})", contact, destination);
+        if let Err(e) = self.outbound.try_send(OutgoingMessage {
+            data: Bytes::from(request),
+            destination,
+            protocol,
+        }) {
+            warn!("Failed to queue MWI NOTIFY to {}: {}", contact, e);
+        }
+    }
+
+    async fn handle_subscribe(&self, request: &SipRequest) -> Result<SipResponse, SipError> {
+        let event = header_value(request, "Event").unwrap_or_default();
+        let package = event.split(';').next().unwrap_or_default().trim();
+        if !package.eq_ignore_ascii_case(MESSAGE_SUMMARY_EVENT) {
+            warn!("Unsupported event package: {}", event);
+            return ResponseBuilder::new(489).build_for_request(request);
+        }
+
+        let call_id = request.call_id().unwrap_or_default();
+        let expires = header_value(request, "Expires")
+            .and_then(|v| v.trim().parse().ok())
+            .unwrap_or(DEFAULT_SUBSCRIPTION_EXPIRES);
+
+        // Refresh or unsubscribe an existing dialog
+        let existing = self.dialogs.lock().unwrap().get(&call_id).copied();
+        if let Some(sub_id) = existing {
+            let subscription = self.subscriptions.get_subscription(&sub_id);
+            if expires == 0 {
+                self.dialogs.lock().unwrap().remove(&call_id);
+                if let Some(mut subscription) = subscription {
+                    subscription.terminate();
+                    self.send_in_dialog(&subscription, &MessageSummary::new(subscription.account.clone()));
+                }
+                let _ = self.subscriptions.unsubscribe(&sub_id);
+            } else if self.subscriptions.refresh_subscription(&sub_id, expires).is_err() {
+                self.dialogs.lock().unwrap().remove(&call_id);
+                return ResponseBuilder::new(481).build_for_request(request);
+            }
+            return ResponseBuilder::new(200)
+                .header(Header::Expires(expires.to_string().into()))
+                .build_for_request(request);
+        }
+
+        if expires == 0 {
+            return ResponseBuilder::new(200).build_for_request(request);
+        }
+
+        let mailbox_id = match aor_user(&request.uri().to_string()) {
+            Some(user) => user.to_string(),
+            None => return ResponseBuilder::new(404).build_for_request(request),
+        };
+        let contact = match contact_uri(request) {
+            Some(contact) => contact,
+            None => return ResponseBuilder::new(400).build_for_request(request),
+        };
+        let from = header_value(request, "From").unwrap_or_default();
+        let subscriber = uri_from_header(&from);
+        let from_tag = tag_param(&from).unwrap_or_default();
+        let to_tag = Uuid::new_v4().simple().to_string();
+
+        let _guard = self.lock_mailbox(&mailbox_id).await;
+        let summary = match self.summary(&mailbox_id).await {
+            Some(summary) => summary,
+            None => return ResponseBuilder::server_internal_error().build_for_request(request),
+        };
+        self.subscriptions.update_summary(summary.clone());
+
+        let sub_id = match self.subscriptions.subscribe(
+            subscriber,
+            summary.account.clone(),
+            contact,
+            expires,
+            call_id.clone(),
+            from_tag,
+            to_tag.clone(),
+        ) {
+            Ok(sub_id) => sub_id,
+            Err(e) => {
+                warn!("Failed to create MWI subscription: {}", e);
+                return ResponseBuilder::new(400).build_for_request(request);
+            }
+        };
+        self.dialogs.lock().unwrap().insert(call_id, sub_id);
+        info!("MWI subscription for mailbox {} ({} s)", mailbox_id, expires);
+
+        // Initial NOTIFY with the current counts
+        if let Some(subscription) = self.subscriptions.get_subscription(&sub_id) {
+            self.send_in_dialog(&subscription, &summary);
+        }
+
+        ResponseBuilder::new(202)
+            .to_tag(&to_tag)
+            .header(Header::Expires(expires.to_string().into()))
+            .build_for_request(request)
+    }
+}
+
+#[async_trait]
+impl SipHandler for MwiNotifier {
+    async fn handle_request(&self, request: SipRequest) -> Result<SipResponse, SipError> {
+        self.handle_subscribe(&request).await
+    }
+
+    fn can_handle(&self, method: SipMethod) -> bool {
+        method == SipMethod::Subscribe
+    }
+}
+
+/// Dialog identifiers of an outgoing NOTIFY
+struct NotifyDialog<'a> {
+    target: &'a str,
+    from: &'a str,
+    from_tag: &'a str,
+    to: &'a str,
+    to_tag: Option<&'a str>,
+    call_id: &'a str,
+    subscription_state: &'a str,
+}
+
+/// Voicemail repository that sends MWI updates after every mailbox change
+pub struct MwiVoicemailRepository {
+    inner: Arc<dyn VoicemailRepository>,
+    notifier: Arc<MwiNotifier>,
+}
+
+impl MwiVoicemailRepository {
+    pub fn new(inner: Arc<dyn VoicemailRepository>, notifier: Arc<MwiNotifier>) -> Self {
+        Self { inner, notifier }
+    }
+}
+
+#[async_trait]
+impl VoicemailRepository for MwiVoicemailRepository {
+    async fn create_message(&self, message: VoicemailMessage) -> Result<VoicemailMessage, String> {
+        let message = self.inner.create_message(message).await?;
+        self.notifier.mailbox_changed(&message.mailbox_id).await;
+        Ok(message)
+    }
+
+    async fn get_message(&self, id: Uuid) -> Result<Option<VoicemailMessage>, String> {
+        self.inner.get_message(id).await
+    }
+
+    async fn list_messages(
+        &self,
+        mailbox_id: &str,
+        status: Option<VoicemailStatus>,
+    ) -> Result<Vec<VoicemailMessage>, String> {
+        self.inner.list_messages(mailbox_id, status).await
+    }
+
+    async fn update_message_status(&self, id: Uuid, status: VoicemailStatus) -> Result<(), String> {
+        let mailbox_id = self.inner.get_message(id).await?.map(|m| m.mailbox_id);
+        self.inner.update_message_status(id, status).await?;
+        if let Some(mailbox_id) = mailbox_id {
+            self.notifier.mailbox_changed(&mailbox_id).await;
+        }
+        Ok(())
+    }
+
+    async fn set_transcript(&self, id: Uuid, transcript: &str, confidence: Option<f32>) -> Result<(), String> {
+        self.inner.set_transcript(id, transcript, confidence).await
+    }
+
+    async fn delete_message(&self, id: Uuid) -> Result<(), String> {
+        let mailbox_id = self.inner.get_message(id).await?.map(|m| m.mailbox_id);
+        self.inner.delete_message(id).await?;
+        if let Some(mailbox_id) = mailbox_id {
+            self.notifier.mailbox_changed(&mailbox_id).await;
+        }
+        Ok(())
+    }
+
+    async fn count_messages(&self, mailbox_id: &str, status: Option<VoicemailStatus>) -> Result<u32, String> {
+        self.inner.count_messages(mailbox_id, status).await
+    }
+
+    async fn count_audio_references(&self, audio_file_path: &str) -> Result<u32, String> {
+        self.inner.count_audio_references(audio_file_path).await
+    }
+
+    async fn get_mailbox(&self, mailbox_id: &str) -> Result<Option<VoicemailMailbox>, String> {
+        self.inner.get_mailbox(mailbox_id).await
+    }
+
+    async fn save_mailbox(&self, mailbox: VoicemailMailbox) -> Result<VoicemailMailbox, String> {
+        self.inner.save_mailbox(mailbox).await
+    }
+}
+
+/// Value of a header by name (case-insensitive)
+fn header_value(request: &SipRequest, name: &str) -> Option<String> {
+    request.headers().iter().find_map(|h| {
+        let line = h.to_string();
+        let (header, value) = line.split_once(':')?;
+        header
+            .trim()
+            .eq_ignore_ascii_case(name)
+            .then(|| value.trim().to_string())
+    })
+}
+
+fn contact_uri(request: &SipRequest) -> Option<String> {
+    header_value(request, "Contact")
+        .map(|contact| uri_from_header(&contact))
+        .filter(|uri| !uri.is_empty())
+}
+
+/// URI of a name-addr header value (`"Bob" <sip:bob@host>;tag=x` -> `sip:bob@host`)
+fn uri_from_header(value: &str) -> String {
+    match (value.find('<'), value.find('>')) {
+        (Some(start), Some(end)) if start < end => value[start + 1..end].to_string(),
+        _ => value.split(';').next().unwrap_or_default().trim().to_string(),
+    }
+}
+
+fn tag_param(value: &str) -> Option<String> {
+    let params = value.rsplit_once('>').map(|(_, p)| p).unwrap_or(value);
+    params
+        .split(';')
+        .find_map(|p| p.trim().strip_prefix("tag="))
+        .map(|tag| tag.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::infrastructure::persistence::MemoryVoicemailRepository;
+
+    fn setup() -> (Arc<Registrar>, Arc<MwiNotifier>, MwiVoicemailRepository, mpsc::Receiver<OutgoingMessage>) {
+        let registrar = Arc::new(Registrar::new());
+        let store: Arc<dyn VoicemailRepository> = Arc::new(MemoryVoicemailRepository::new());
+        let (tx, rx) = mpsc::channel(16);
+        let notifier = Arc::new(MwiNotifier::new(
+            registrar.clone(),
+            store.clone(),
+            tx,
+            "example.com".to_string(),
+            "192.0.2.1:5060".to_string(),
+        ));
+        let repository = MwiVoicemailRepository::new(store, notifier.clone());
+        (registrar, notifier, repository, rx)
+    }
+
+    fn voicemail(mailbox: &str) -> VoicemailMessage {
+        VoicemailMessage::new(
+            mailbox.to_string(),
+            "sip:carol@example.com".to_string(),
+            None,
+            12,
+            "vm.wav".to_string(),
+            "wav".to_string(),
+        )
+    }
+
+    fn body(message: &OutgoingMessage) -> String {
+        let request = SipRequest::parse(&message.data).unwrap();
+        String::from_utf8_lossy(request.body()).to_string()
+    }
+
+    #[tokio::test]
+    async fn test_mailbox_changes_update_lamp() {
+        let (registrar, _notifier, repository, mut rx) = setup();
+        registrar
+            .add_binding("sip:bob@example.com".to_string(), "sip:bob@10.0.0.7:5062".to_string(), 3600)
+            .await
+            .unwrap();
+
+        let first = repository.create_message(voicemail("bob")).await.unwrap();
+        let second = repository.create_message(voicemail("bob")).await.unwrap();
+        let third = repository.create_message(voicemail("bob")).await.unwrap();
+        repository
+            .update_message_status(third.id, VoicemailStatus::Read)
+            .await
+            .unwrap();
+
+        let mut last = None;
+        while let Ok(message) = rx.try_recv() {
+            last = Some(message);
+        }
+        let last = last.unwrap();
+        assert_eq!(last.destination, "10.0.0.7:5062".parse().unwrap());
+        let text = body(&last);
+        assert!(text.contains("Messages-Waiting: yes"));
+        assert!(text.contains("Voice-Message: 2/1"));
+
+        // Read one, delete the last unread one: lamp goes off
+        repository
+            .update_message_status(first.id, VoicemailStatus::Read)
+            .await
+            .unwrap();
+        assert!(body(&rx.try_recv().unwrap()).contains("Voice-Message: 1/2"));
+
+        repository.delete_message(second.id).await.unwrap();
+        let text = body(&rx.try_recv().unwrap());
+        assert!(text.contains("Messages-Waiting: no"));
+        assert!(text.contains("Voice-Message: 0/2"));
+    }
+
+    #[tokio::test]
+    async fn test_register_with_unread_messages() {
+        let (registrar, notifier, repository, mut rx) = setup();
+        let listener = notifier.clone().spawn_registration_listener();
+
+        // Nothing waiting: no NOTIFY on register
+        registrar
+            .add_binding("sip:alice@example.com".to_string(), "sip:alice@10.0.0.5:5060".to_string(), 3600)
+            .await
+            .unwrap();
+        notifier.contact_registered("sip:alice@example.com", "sip:alice@10.0.0.5:5060").await;
+        tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+        assert!(rx.try_recv().is_err());
+
+        // Deposit while offline, then a new contact registers
+        repository.create_message(voicemail("alice")).await.unwrap();
+        while rx.try_recv().is_ok() {}
+        registrar
+            .add_binding("sip:alice@example.com".to_string(), "sip:alice@10.0.0.6:5060".to_string(), 3600)
+            .await
+            .unwrap();
+
+        let message = tokio::time::timeout(std::time::Duration::from_secs(1), rx.recv())
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(message.destination, "10.0.0.6:5060".parse().unwrap());
+        assert!(body(&message).contains("Voice-Message: 1/0"));
+        listener.abort();
+    }
+
+    #[tokio::test]
+    async fn test_list_message_reaches_every_member() {
+        use crate::domain::voicemail_list::{VoicemailDistributionList, VoicemailListService};
+        use crate::domain::voicemail_service::{Deposit, VoicemailService};
+        use crate::infrastructure::persistence::MemoryVoicemailListRepository;
+
+        let (registrar, _notifier, repository, mut rx) = setup();
+        for (user, port) in [("alice", 5061), ("bob", 5062), ("carol", 5063)] {
+            registrar
+                .add_binding(
+                    format!("sip:{}@example.com", user),
+                    format!("sip:{}@10.0.0.9:{}", user, port),
+                    3600,
+                )
+                .await
+                .unwrap();
+        }
+        let repository: Arc<dyn VoicemailRepository> = Arc::new(repository);
+        let lists = VoicemailListService::new(
+            Arc::new(MemoryVoicemailListRepository::new()),
+            repository.clone(),
+        );
+        lists
+            .create(
+                VoicemailDistributionList::new(
+                    "Sales Team".to_string(),
+                    "801".to_string(),
+                    "manager".to_string(),
+                )
+                .with_member("alice")
+                .with_member("bob")
+                .with_member("carol"),
+            )
+            .await
+            .unwrap();
+
+        // The manager records once and addresses the list from the menu
+        let dir = std::env::temp_dir().join(format!("yakyak-vm-list-{}", Uuid::new_v4()));
+        let service = VoicemailService::new(&dir);
+        let list = lists.resolve("801", "manager").await.unwrap();
+        assert!(lists.resolve("801", "alice").await.is_err());
+        let sender = VoicemailMailbox::new("manager".to_string(), 1);
+        let mut recorder = match service.begin_list_deposit(&sender) {
+            Deposit::Record(recorder) => recorder,
+            Deposit::Refused { .. } => panic!("list message refused"),
+        };
+        recorder.start();
+        recorder.add_samples(&[0i16; 160]).unwrap();
+        recorder.stop();
+        let message = service
+            .save_list_recording(
+                &list,
+                "sip:manager@example.com".to_string(),
+                Some("Dana".to_string()),
+                &recorder,
+            )
+            .unwrap();
+
+        let delivered = lists.deliver(&list, &message).await.unwrap();
+        assert_eq!(delivered.len(), 3);
+        for member in ["alice", "bob", "carol"] {
+            let messages = repository.list_messages(member, None).await.unwrap();
+            assert_eq!(messages.len(), 1);
+            assert_eq!(messages[0].audio_file_path, message.audio_file_path);
+            assert_eq!(messages[0].display_caller(), "Dana (via Sales Team)");
+        }
+        let stored = std::fs::read_dir(dir.join("lists").join(list.id.to_string()))
+            .unwrap()
+            .count();
+        assert_eq!(stored, 1);
+        assert_eq!(
+            repository.count_audio_references(&message.audio_file_path).await.unwrap(),
+            3
+        );
+
+        let mut lamps = Vec::new();
+        while let Ok(notify) = rx.try_recv() {
+            assert!(body(&notify).contains("Voice-Message: 1/0"));
+            lamps.push(notify.destination.port());
+        }
+        lamps.sort();
+        assert_eq!(lamps, vec![5061, 5062, 5063]);
+
+        // Leaving the list keeps the delivered message
+        lists
+            .update(
+                list.id,
+                VoicemailDistributionList {
+                    members: vec!["alice".to_string()],
+                    ..list.clone()
+                },
+            )
+            .await
+            .unwrap();
+        assert_eq!(repository.list_messages("carol", None).await.unwrap().len(), 1);
+
+        // The recording goes with the last copy
+        let audio = dir.join(&message.audio_file_path);
+        for (i, copy) in delivered.iter().enumerate() {
+            assert!(audio.exists(), "recording deleted after {} copies", i);
+            service.delete_message(repository.as_ref(), copy).await.unwrap();
+        }
+        assert!(!audio.exists());
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[tokio::test]
+    async fn test_subscribe_gets_initial_notify() {
+        let (_registrar, notifier, repository, mut rx) = setup();
+        repository.create_message(voicemail("bob")).await.unwrap();
+
+        let subscribe = "SUBSCRIBE sip:bob@example.com SIP/2.0\r\n\
+            Via: SIP/2.0/UDP 10.0.0.7:5062;branch=z9hG4bKsub1\r\n\
+            From: <sip:bob@example.com>;tag=phone1\r\n\
+            To: <sip:bob@example.com>\r\n\
+            Call-ID: mwi-sub-1\r\n\
+            CSeq: 1 SUBSCRIBE\r\n\
+            Contact: <sip:bob@10.0.0.7:5062>\r\n\
+            Event: message-summary\r\n\
+            Expires: 600\r\n\
+            Content-Length: 0\r\n\r\n";
+        let request = SipRequest::parse(subscribe.as_bytes()).unwrap();
+        let response = notifier.handle_request(request).await.unwrap();
+        assert_eq!(response.status_code(), 202);
+
+        let notify = rx.try_recv().unwrap();
+        let text = String::from_utf8_lossy(&notify.data).to_string();
+        assert!(text.contains("Call-ID: mwi-sub-1"));
+        assert!(text.contains(";tag=phone1"));
+        assert!(text.contains("Subscription-State: active"));
+        assert!(body(&notify).contains("Messages-Waiting: yes"));
+    }
+}
@@ -0,0 +1,195 @@
+//! SIP OPTIONS handling
+//!
+//! Phones, SBCs and trunk peers send OPTIONS to check the server is alive
+//! and to learn what it supports (RFC 3261 Section 11). Pings outside a
+//! dialog are always answered 200 with the methods, body types and
+//! extensions we accept. An OPTIONS inside a dialog (with a To tag) gets
+//! the same answer while the dialog's call is active, 481 otherwise.
+
+use super::builder::ResponseBuilder;
+use super::call_router::CallRouter;
+use super::handler::SipHandler;
+use super::message::{SipError, SipMethod, SipRequest, SipResponse};
+use async_trait::async_trait;
+use rsip::Header;
+use std::sync::Arc;
+use tracing::debug;
+
+/// Body types accepted in requests
+pub const ACCEPTED_CONTENT_TYPES: &str = "application/sdp";
+
+/// Extensions supported: INVITE with Replaces (call takeover)
+pub const SUPPORTED_EXTENSIONS: &str = "replaces";
+
+/// Order methods are listed in Allow
+const METHOD_ORDER: [SipMethod; 14] = [
+    SipMethod::Invite,
+    SipMethod::Ack,
+    SipMethod::Cancel,
+    SipMethod::Bye,
+    SipMethod::Options,
+    SipMethod::Register,
+    SipMethod::Subscribe,
+    SipMethod::Notify,
+    SipMethod::Refer,
+    SipMethod::Info,
+    SipMethod::Message,
+    SipMethod::Update,
+    SipMethod::Prack,
+    SipMethod::Publish,
+];
+
+/// Allow value listing `methods`, the methods handlers are registered for;
+/// OPTIONS itself is always listed
+pub fn allow_header(methods: &[SipMethod]) -> String {
+    METHOD_ORDER
+        .iter()
+        .filter(|method| **method == SipMethod::Options || methods.contains(method))
+        .map(SipMethod::as_str)
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+/// OPTIONS handler
+pub struct OptionsHandler {
+    call_router: Arc<CallRouter>,
+    /// Value of the Allow header
+    allow: String,
+}
+
+impl OptionsHandler {
+    /// Answer with `allow`, built by [`allow_header`], in Allow
+    pub fn new(call_router: Arc<CallRouter>, allow: String) -> Self {
+        Self { call_router, allow }
+    }
+
+    /// Value of the Allow header
+    pub fn allow(&self) -> &str {
+        &self.allow
+    }
+}
+
+#[async_trait]
+impl SipHandler for OptionsHandler {
+    async fn handle_request(&self, request: SipRequest) -> Result<SipResponse, SipError> {
+        if request.to_tag().is_some() {
+            let call_id = request.call_id().unwrap_or_default();
+            let call_id = self.call_router.canonical_call_id(&call_id).await;
+            match self.call_router.get_call_state(&call_id).await {
+                Some(state) if state.is_active() => {}
+                _ => {
+                    debug!("OPTIONS for unknown dialog of call {}", call_id);
+                    return ResponseBuilder::new(481).build_for_request(&request);
+                }
+            }
+        }
+
+        ResponseBuilder::ok()
+            .header(Header::Allow(self.allow.clone().into()))
+            .header(Header::Accept(ACCEPTED_CONTENT_TYPES.into()))
+            .header(Header::Supported(SUPPORTED_EXTENSIONS.into()))
+            .build_for_request(&request)
+    }
+
+    fn can_handle(&self, method: SipMethod) -> bool {
+        matches!(method, SipMethod::Options)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::infrastructure::protocols::sip::registrar::Registrar;
+    use rsip::headers::UntypedHeader;
+
+    const METHODS: [SipMethod; 8] = [
+        SipMethod::Register,
+        SipMethod::Invite,
+        SipMethod::Ack,
+        SipMethod::Cancel,
+        SipMethod::Bye,
+        SipMethod::Info,
+        SipMethod::Subscribe,
+        SipMethod::Message,
+    ];
+
+    fn options_request(call_id: &str, to_tag: Option<&str>) -> SipRequest {
+        let to_tag = to_tag.map(|tag| format!(";tag={}", tag)).unwrap_or_default();
+        let request = format!(
+            "OPTIONS sip:example.com SIP/2.0\r\n\
+             Via: SIP/2.0/UDP 192.0.2.10:5060;branch=z9hG4bKping{call_id}\r\n\
+             Max-Forwards: 70\r\n\
+             From: <sip:sbc@carrier.example>;tag=sbc1\r\n\
+             To: <sip:example.com>{to_tag}\r\n\
+             Call-ID: {call_id}\r\n\
+             CSeq: 1 OPTIONS\r\n\
+             Accept: application/sdp\r\n\
+             Content-Length: 0\r\n\
+             \r\n"
+        );
+        SipRequest::parse(request.as_bytes()).unwrap()
+    }
+
+    fn header(response: &SipResponse, name: &str) -> Option<String> {
+        response.headers().iter().find_map(|h| match h {
+            Header::Allow(allow) if name == "Allow" => Some(allow.value().to_string()),
+            Header::Accept(accept) if name == "Accept" => Some(accept.value().to_string()),
+            Header::Supported(supported) if name == "Supported" => {
+                Some(supported.value().to_string())
+            }
+            _ => None,
+        })
+    }
+
+    fn handler() -> (OptionsHandler, Arc<CallRouter>) {
+        let router = Arc::new(CallRouter::new(Arc::new(Registrar::new())));
+        (OptionsHandler::new(router.clone(), allow_header(&METHODS)), router)
+    }
+
+    #[tokio::test]
+    async fn test_ping_lists_registered_methods() {
+        let (handler, _) = handler();
+
+        let response = handler
+            .handle_request(options_request("ping-1", None))
+            .await
+            .unwrap();
+
+        assert_eq!(response.status_code(), 200);
+        assert_eq!(
+            header(&response, "Allow").as_deref(),
+            Some("INVITE, ACK, CANCEL, BYE, OPTIONS, REGISTER, SUBSCRIBE, INFO, MESSAGE")
+        );
+        assert_eq!(header(&response, "Accept").as_deref(), Some("application/sdp"));
+        assert_eq!(header(&response, "Supported").as_deref(), Some("replaces"));
+        assert!(handler.can_handle(SipMethod::Options));
+        assert!(!handler.can_handle(SipMethod::Invite));
+    }
+
+    #[tokio::test]
+    async fn test_in_dialog_options_needs_active_call() {
+        let (handler, router) = handler();
+        router
+            .create_call(
+                "call-1".to_string(),
+                "sip:sbc@carrier.example".to_string(),
+                "sip:alice@example.com".to_string(),
+            )
+            .await
+            .unwrap();
+        router.answer_call("call-1").await.unwrap();
+
+        let response = handler
+            .handle_request(options_request("call-1", Some("yak1")))
+            .await
+            .unwrap();
+        assert_eq!(response.status_code(), 200);
+        assert!(header(&response, "Allow").unwrap().contains("BYE"));
+
+        let response = handler
+            .handle_request(options_request("call-2", Some("yak1")))
+            .await
+            .unwrap();
+        assert_eq!(response.status_code(), 481);
+    }
+}
@@ -0,0 +1,1150 @@
+//! Outbound registration to upstream providers
+//!
+//! Some trunk providers want the PBX to register like a phone instead of
+//! trusting its IP address. [`OutboundRegistration`] keeps every trunk
+//! with `register_enabled` registered: REGISTER goes out with the trunk's
+//! credentials, 401/407 challenges are answered, 423 is retried with the
+//! provider's Min-Expires, the binding is refreshed after a fraction of
+//! the granted expiry and failures are retried with exponential backoff
+//! and jitter. Bindings are removed on shutdown.
+//!
+//! Responses arrive through `SipServer::subscribe_responses`, matched by
+//! the per-trunk Call-ID.
+//!
+//! With a [`SipResolver`] the registrar is found through its SRV records
+//! (UDP); a registrar that does not answer or answers 503 is tried last at
+//! the next attempt.
+
+use super::address::uri_from_header;
+use super::advertise::AddressAdvertiser;
+use super::auth::AuthChallenge;
+use super::hops::DEFAULT_MAX_FORWARDS;
+use super::message::SipResponse;
+use super::resolver::{SipResolver, SipTarget};
+use super::transaction::{SipTimerConfig, TimerType};
+use super::transport::{OutgoingMessage, TransportProtocol};
+use crate::domain::shared::SecretBox;
+use crate::domain::sip_trunk::{SipTrunk, SipTrunkRepository};
+use bytes::Bytes;
+use chrono::{DateTime, Duration, Utc};
+use metrics::{counter, gauge};
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use tokio::sync::{broadcast, mpsc};
+use tokio::task::JoinHandle;
+use tracing::{debug, info, warn};
+use uuid::Uuid;
+
+/// Refresh and retry settings of outbound registrations
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct OutboundRegistrationPolicy {
+    /// Fraction of the granted expiry after which the binding is refreshed
+    pub refresh_fraction: f64,
+    /// Delay before the first retry after a failure; doubled per failure
+    pub retry_initial_secs: u64,
+    /// Longest delay between retries
+    pub retry_max_secs: u64,
+    /// Random spread of retry delays, as a fraction of the delay
+    pub retry_jitter: f64,
+    /// REGISTERs without a final response after this long have failed;
+    /// unset, the trunk's Timer F of `[sip.timers]`
+    pub response_timeout_secs: Option<u64>,
+}
+
+impl Default for OutboundRegistrationPolicy {
+    fn default() -> Self {
+        Self {
+            refresh_fraction: 0.8,
+            retry_initial_secs: 30,
+            retry_max_secs: 1800,
+            retry_jitter: 0.2,
+            response_timeout_secs: None,
+        }
+    }
+}
+
+impl OutboundRegistrationPolicy {
+    /// Delay before the next attempt after `failures` consecutive failures,
+    /// before jitter
+    pub fn retry_delay(&self, failures: u32) -> Duration {
+        let factor = 1u64 << failures.saturating_sub(1).min(16);
+        let secs = self
+            .retry_initial_secs
+            .saturating_mul(factor)
+            .min(self.retry_max_secs);
+        Duration::seconds(secs as i64)
+    }
+
+    fn jittered(&self, delay: Duration) -> Duration {
+        let spread = (delay.num_milliseconds() as f64 * self.retry_jitter.clamp(0.0, 1.0)) as i64;
+        if spread == 0 {
+            return delay;
+        }
+        delay + Duration::milliseconds(rand::thread_rng().gen_range(-spread..=spread))
+    }
+
+    /// When to refresh a binding granted for `expires` seconds
+    pub fn refresh_after(&self, expires: u32) -> Duration {
+        let fraction = self.refresh_fraction.clamp(0.1, 1.0);
+        Duration::milliseconds((expires as f64 * 1000.0 * fraction) as i64)
+    }
+}
+
+/// Registration state of one trunk
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(tag = "state", rename_all = "snake_case")]
+pub enum RegistrationState {
+    /// Not registered yet, or unregistered on shutdown
+    Unregistered,
+    /// REGISTER sent, waiting for the provider
+    Registering,
+    Registered {
+        expires_at: DateTime<Utc>,
+        refresh_at: DateTime<Utc>,
+    },
+    /// The last attempt failed; `last_status` is `None` when the provider
+    /// could not be reached
+    Retrying {
+        last_status: Option<u16>,
+        failures: u32,
+        next_attempt_at: DateTime<Utc>,
+    },
+    /// The provider refused the credentials; not retried until
+    /// registration is triggered again
+    Failed { last_status: u16 },
+}
+
+/// Registration state of a trunk, as shown by the API
+#[derive(Debug, Clone, Serialize)]
+pub struct TrunkRegistrationStatus {
+    pub trunk_id: Uuid,
+    pub trunk_name: String,
+    pub registrar: String,
+    #[serde(flatten)]
+    pub state: RegistrationState,
+    /// Resolved registrar that accepted the last registration
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub registrar_address: Option<String>,
+    pub last_registration: Option<DateTime<Utc>>,
+}
+
+/// Registration of one trunk
+struct Binding {
+    trunk: SipTrunk,
+    /// Kept for the life of the binding so refreshes update it (RFC 3261 10.2.4)
+    call_id: String,
+    from_tag: String,
+    cseq: u32,
+    /// Contact URI of the last REGISTER
+    contact: String,
+    /// Expiry asked for; raised to the provider's Min-Expires after a 423
+    expires: u32,
+    /// Last challenge and whether it came from a proxy (407)
+    challenge: Option<(AuthChallenge, bool)>,
+    nonce_count: u32,
+    /// Whether the pending REGISTER answers a challenge
+    sent_credentials: bool,
+    /// CSeq and send time of the REGISTER awaiting a final response
+    pending: Option<(u32, DateTime<Utc>)>,
+    /// The pending REGISTER removes the binding
+    unregistering: bool,
+    /// Resolved registrar the pending REGISTER went to
+    target: Option<SipTarget>,
+    /// Resolved registrar that accepted the last registration
+    served_by: Option<SipTarget>,
+    failures: u32,
+    state: RegistrationState,
+}
+
+impl Binding {
+    fn new(trunk: SipTrunk, domain: &str) -> Self {
+        Self {
+            expires: trunk.register_expiry,
+            trunk,
+            call_id: format!("{}@{}", Uuid::new_v4(), domain),
+            from_tag: Uuid::new_v4().simple().to_string(),
+            cseq: 0,
+            contact: String::new(),
+            challenge: None,
+            nonce_count: 0,
+            sent_credentials: false,
+            pending: None,
+            unregistering: false,
+            target: None,
+            served_by: None,
+            failures: 0,
+            state: RegistrationState::Unregistered,
+        }
+    }
+
+    fn is_due(&self, now: DateTime<Utc>) -> bool {
+        if self.pending.is_some() {
+            return false;
+        }
+        match &self.state {
+            RegistrationState::Unregistered => true,
+            RegistrationState::Registered { refresh_at, .. } => *refresh_at <= now,
+            RegistrationState::Retrying {
+                next_attempt_at, ..
+            } => *next_attempt_at <= now,
+            RegistrationState::Registering | RegistrationState::Failed { .. } => false,
+        }
+    }
+
+    fn registrar_uri(&self) -> String {
+        if self.trunk.sip_port == 5060 {
+            format!("sip:{}", self.trunk.sip_server)
+        } else {
+            format!("sip:{}:{}", self.trunk.sip_server, self.trunk.sip_port)
+        }
+    }
+
+    fn status(&self) -> TrunkRegistrationStatus {
+        TrunkRegistrationStatus {
+            trunk_id: self.trunk.id,
+            trunk_name: self.trunk.name.clone(),
+            registrar: self.registrar_uri(),
+            state: self.state.clone(),
+            registrar_address: self.served_by.as_ref().map(ToString::to_string),
+            last_registration: self.trunk.last_registration,
+        }
+    }
+}
+
+/// What a response asks of the trunk's registration
+enum Next {
+    Nothing,
+    /// Send the REGISTER again right away (challenge or 423 answered)
+    Resend,
+    /// The trunk's registered flag changed
+    Persist(Box<SipTrunk>),
+}
+
+/// Keeps trunks registered with their upstream providers
+pub struct OutboundRegistration {
+    outbound: mpsc::Sender<OutgoingMessage>,
+    /// `host:port` of the local listener, for Via and Contact
+    local_addr: String,
+    /// External address used instead of `local_addr` behind NAT
+    advertiser: Option<Arc<AddressAdvertiser>>,
+    /// Where the trunks' registered flag is kept
+    repository: Option<Arc<dyn SipTrunkRepository>>,
+    policy: OutboundRegistrationPolicy,
+    /// SRV resolution and failover of registrars
+    resolver: Option<Arc<SipResolver>>,
+    /// Transaction timers, for the REGISTER timeout
+    timers: SipTimerConfig,
+    bindings: Mutex<HashMap<Uuid, Binding>>,
+    /// Set on shutdown: nothing is registered any more
+    stopping: AtomicBool,
+}
+
+impl OutboundRegistration {
+    pub fn new(outbound: mpsc::Sender<OutgoingMessage>, local_addr: String) -> Self {
+        Self {
+            outbound,
+            local_addr,
+            advertiser: None,
+            repository: None,
+            policy: OutboundRegistrationPolicy::default(),
+            resolver: None,
+            timers: SipTimerConfig::default(),
+            bindings: Mutex::new(HashMap::new()),
+            stopping: AtomicBool::new(false),
+        }
+    }
+
+    pub fn with_policy(mut self, policy: OutboundRegistrationPolicy) -> Self {
+        self.policy = policy;
+        self
+    }
+
+    /// Register the external address as Contact with providers outside the
+    /// local subnets
+    pub fn with_address_advertiser(mut self, advertiser: Arc<AddressAdvertiser>) -> Self {
+        self.advertiser = Some(advertiser);
+        self
+    }
+
+    /// Find registrars through their SRV records, failing over to the next
+    /// one when a registrar does not answer
+    pub fn with_resolver(mut self, resolver: Arc<SipResolver>) -> Self {
+        self.resolver = Some(resolver);
+        self
+    }
+
+    /// Time REGISTERs out after Timer F of these timers
+    pub fn with_timer_config(mut self, timers: SipTimerConfig) -> Self {
+        self.timers = timers;
+        self
+    }
+
+    /// Record registrations on the stored trunks
+    pub fn with_repository(mut self, repository: Arc<dyn SipTrunkRepository>) -> Self {
+        self.repository = Some(repository);
+        self
+    }
+
+    /// Start registering `trunk`; returns false for trunks that do not
+    /// register or have no username
+    pub fn add_trunk(&self, trunk: SipTrunk) -> bool {
+        if !trunk.enabled || !trunk.register_enabled {
+            return false;
+        }
+        if trunk.username.is_none() {
+            warn!("Trunk {} registers but has no username", trunk.name);
+            return false;
+        }
+        let domain = self.local_domain().to_string();
+        info!("Registering trunk {} with {}", trunk.name, trunk.sip_server);
+        self.bindings
+            .lock()
+            .unwrap()
+            .insert(trunk.id, Binding::new(trunk, &domain));
+        true
+    }
+
+    /// Register `trunk_id` again at the next check, clearing failures
+    pub fn register_now(&self, trunk_id: Uuid) -> bool {
+        let mut bindings = self.bindings.lock().unwrap();
+        match bindings.get_mut(&trunk_id) {
+            Some(binding) => {
+                binding.failures = 0;
+                binding.pending = None;
+                binding.state = RegistrationState::Unregistered;
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Registration state of every registering trunk, by name
+    pub fn statuses(&self) -> Vec<TrunkRegistrationStatus> {
+        let mut statuses: Vec<_> = self
+            .bindings
+            .lock()
+            .unwrap()
+            .values()
+            .map(Binding::status)
+            .collect();
+        statuses.sort_by(|a, b| a.trunk_name.cmp(&b.trunk_name));
+        statuses
+    }
+
+    pub fn status(&self, trunk_id: Uuid) -> Option<TrunkRegistrationStatus> {
+        self.bindings
+            .lock()
+            .unwrap()
+            .get(&trunk_id)
+            .map(Binding::status)
+    }
+
+    /// Send due REGISTERs every `tick`
+    pub fn spawn(self: Arc<Self>, tick: std::time::Duration) -> JoinHandle<()> {
+        tokio::spawn(async move {
+            let mut ticker = tokio::time::interval(tick);
+            loop {
+                ticker.tick().await;
+                self.poll_at(Utc::now()).await;
+            }
+        })
+    }
+
+    /// Handle responses to our REGISTERs (see `SipServer::subscribe_responses`)
+    pub fn spawn_response_listener(
+        self: Arc<Self>,
+        mut responses: broadcast::Receiver<SipResponse>,
+    ) -> JoinHandle<()> {
+        tokio::spawn(async move {
+            loop {
+                match responses.recv().await {
+                    Ok(response) => self.handle_response(&response).await,
+                    Err(broadcast::error::RecvError::Lagged(skipped)) => {
+                        warn!("Outbound registration missed {} responses", skipped);
+                    }
+                    Err(broadcast::error::RecvError::Closed) => break,
+                }
+            }
+        })
+    }
+
+    /// Time out unanswered REGISTERs and send the due ones as of `now`
+    pub async fn poll_at(&self, now: DateTime<Utc>) {
+        if self.stopping.load(Ordering::Relaxed) {
+            return;
+        }
+        let due: Vec<Uuid> = {
+            let mut bindings = self.bindings.lock().unwrap();
+            for binding in bindings.values_mut() {
+                let timeout = self.response_timeout(&binding.trunk);
+                if binding
+                    .pending
+                    .is_some_and(|(_, sent_at)| now - sent_at >= timeout)
+                {
+                    debug!("REGISTER to {} timed out", binding.trunk.sip_server);
+                    binding.pending = None;
+                    if let (Some(resolver), Some(target)) = (&self.resolver, binding.target.take())
+                    {
+                        resolver.mark_failed(&target);
+                    }
+                    if binding.unregistering {
+                        binding.unregistering = false;
+                        binding.state = RegistrationState::Unregistered;
+                    } else {
+                        self.fail(binding, None, now);
+                    }
+                }
+            }
+            bindings
+                .values()
+                .filter(|binding| binding.is_due(now))
+                .map(|binding| binding.trunk.id)
+                .collect()
+        };
+        for trunk_id in due {
+            self.send_register(trunk_id, false, now).await;
+        }
+    }
+
+    /// Remove every binding, waiting up to `wait` for the providers
+    pub async fn unregister_all(&self, wait: std::time::Duration) {
+        self.stopping.store(true, Ordering::Relaxed);
+        let registered: Vec<Uuid> = self
+            .bindings
+            .lock()
+            .unwrap()
+            .values()
+            .filter(|binding| matches!(binding.state, RegistrationState::Registered { .. }))
+            .map(|binding| binding.trunk.id)
+            .collect();
+        if registered.is_empty() {
+            return;
+        }
+        info!("Unregistering {} trunks", registered.len());
+        for trunk_id in registered {
+            self.send_register(trunk_id, true, Utc::now()).await;
+        }
+
+        let deadline = tokio::time::Instant::now() + wait;
+        while tokio::time::Instant::now() < deadline {
+            let waiting = self
+                .bindings
+                .lock()
+                .unwrap()
+                .values()
+                .any(|binding| binding.unregistering);
+            if !waiting {
+                return;
+            }
+            tokio::time::sleep(std::time::Duration::from_millis(20)).await;
+        }
+        warn!("Not every provider confirmed the unregistration");
+    }
+
+    /// A provider answered one of our REGISTERs
+    pub async fn handle_response(&self, response: &SipResponse) {
+        let status = response.status_code();
+        if status < 200 {
+            return;
+        }
+        let (cseq, is_register) = match response_header(response, "CSeq") {
+            Some(cseq) => {
+                let mut parts = cseq.split_whitespace();
+                let number = parts.next().and_then(|n| n.parse::<u32>().ok());
+                (number, parts.next() == Some("REGISTER"))
+            }
+            None => return,
+        };
+        let call_id = match response_header(response, "Call-ID") {
+            Some(call_id) if is_register => call_id,
+            _ => return,
+        };
+
+        let now = Utc::now();
+        let (trunk_id, next) = {
+            let mut bindings = self.bindings.lock().unwrap();
+            let binding = match bindings.values_mut().find(|b| b.call_id == call_id) {
+                Some(binding) => binding,
+                None => return,
+            };
+            // Retransmitted or late responses to an earlier REGISTER
+            if binding.pending.map(|(pending, _)| pending) != cseq {
+                return;
+            }
+            binding.pending = None;
+            if let (Some(resolver), Some(target)) = (&self.resolver, binding.target.take()) {
+                if status == 503 {
+                    resolver.mark_failed(&target);
+                } else {
+                    resolver.mark_succeeded(&target);
+                    if (200..300).contains(&status) && !binding.unregistering {
+                        binding.served_by = Some(target);
+                    }
+                }
+            }
+            (
+                binding.trunk.id,
+                self.apply_response(binding, response, now),
+            )
+        };
+
+        match next {
+            Next::Nothing => {}
+            Next::Resend => {
+                let unregistering = self
+                    .bindings
+                    .lock()
+                    .unwrap()
+                    .get(&trunk_id)
+                    .is_some_and(|binding| binding.unregistering);
+                self.send_register(trunk_id, unregistering, now).await;
+            }
+            Next::Persist(trunk) => {
+                if let Some(repository) = &self.repository {
+                    if let Err(e) = repository.update_trunk(&trunk).await {
+                        warn!(
+                            "Failed to record registration of trunk {}: {}",
+                            trunk.name, e
+                        );
+                    }
+                }
+            }
+        }
+    }
+
+    fn apply_response(
+        &self,
+        binding: &mut Binding,
+        response: &SipResponse,
+        now: DateTime<Utc>,
+    ) -> Next {
+        let status = response.status_code();
+        match status {
+            200..=299 if binding.unregistering => {
+                binding.unregistering = false;
+                binding.state = RegistrationState::Unregistered;
+                binding.trunk.mark_unregistered();
+                gauge!("sip_trunk_registered", "trunk" => binding.trunk.name.clone()).set(0.0);
+                info!("Trunk {} unregistered", binding.trunk.name);
+                Next::Persist(Box::new(binding.trunk.clone()))
+            }
+            200..=299 => {
+                let granted =
+                    granted_expires(response, &binding.contact).unwrap_or(binding.expires);
+                let was_registered = binding.trunk.registered;
+                binding.failures = 0;
+                binding.state = RegistrationState::Registered {
+                    expires_at: now + Duration::seconds(granted as i64),
+                    refresh_at: now + self.policy.refresh_after(granted),
+                };
+                binding.trunk.mark_registered();
+                gauge!("sip_trunk_registered", "trunk" => binding.trunk.name.clone()).set(1.0);
+                if was_registered {
+                    debug!(
+                        "Trunk {} registration refreshed for {}s",
+                        binding.trunk.name, granted
+                    );
+                } else {
+                    info!("Trunk {} registered for {}s", binding.trunk.name, granted);
+                }
+                Next::Persist(Box::new(binding.trunk.clone()))
+            }
+            401 | 407 => {
+                let header = if status == 401 {
+                    "WWW-Authenticate"
+                } else {
+                    "Proxy-Authenticate"
+                };
+                let challenge =
+                    response_header(response, header).and_then(|v| AuthChallenge::parse(&v));
+                match challenge {
+                    // A fresh challenge to our credentials means they were refused
+                    Some(challenge) if !binding.sent_credentials || challenge.stale => {
+                        binding.challenge = Some((challenge, status == 407));
+                        binding.nonce_count = 0;
+                        Next::Resend
+                    }
+                    _ => {
+                        warn!(
+                            "Provider {} refused the credentials of trunk {} ({})",
+                            binding.trunk.sip_server, binding.trunk.name, status
+                        );
+                        self.give_up(binding, status);
+                        Next::Nothing
+                    }
+                }
+            }
+            423 => {
+                match response_header(response, "Min-Expires").and_then(|v| v.parse::<u32>().ok()) {
+                    Some(min_expires) if min_expires > binding.expires => {
+                        debug!(
+                            "Trunk {} expiry raised to {}s by the provider",
+                            binding.trunk.name, min_expires
+                        );
+                        binding.expires = min_expires;
+                        Next::Resend
+                    }
+                    _ => {
+                        self.fail(binding, Some(status), now);
+                        Next::Nothing
+                    }
+                }
+            }
+            403 | 404 => {
+                warn!(
+                    "Provider {} refused the registration of trunk {} ({})",
+                    binding.trunk.sip_server, binding.trunk.name, status
+                );
+                self.give_up(binding, status);
+                Next::Nothing
+            }
+            _ => {
+                self.fail(binding, Some(status), now);
+                Next::Nothing
+            }
+        }
+    }
+
+    /// Schedule a retry with backoff
+    fn fail(&self, binding: &mut Binding, status: Option<u16>, now: DateTime<Utc>) {
+        binding.failures += 1;
+        let delay = self
+            .policy
+            .jittered(self.policy.retry_delay(binding.failures));
+        warn!(
+            "Registration of trunk {} failed ({}), retrying in {}s",
+            binding.trunk.name,
+            status.map_or_else(|| "no response".to_string(), |s| s.to_string()),
+            delay.num_seconds()
+        );
+        binding.state = RegistrationState::Retrying {
+            last_status: status,
+            failures: binding.failures,
+            next_attempt_at: now + delay,
+        };
+        binding.trunk.registered = false;
+        counter!("sip_trunk_registration_failures_total", "trunk" => binding.trunk.name.clone())
+            .increment(1);
+        gauge!("sip_trunk_registered", "trunk" => binding.trunk.name.clone()).set(0.0);
+    }
+
+    fn give_up(&self, binding: &mut Binding, status: u16) {
+        binding.challenge = None;
+        binding.state = RegistrationState::Failed {
+            last_status: status,
+        };
+        binding.trunk.registered = false;
+        counter!("sip_trunk_registration_failures_total", "trunk" => binding.trunk.name.clone())
+            .increment(1);
+        gauge!("sip_trunk_registered", "trunk" => binding.trunk.name.clone()).set(0.0);
+    }
+
+    async fn send_register(&self, trunk_id: Uuid, unregister: bool, now: DateTime<Utc>) {
+        let (host, port, registrar) = match self.bindings.lock().unwrap().get(&trunk_id) {
+            Some(binding) => (
+                binding.trunk.sip_server.clone(),
+                binding.trunk.sip_port,
+                binding.registrar_uri(),
+            ),
+            None => return,
+        };
+        let (destination, target) = match &self.resolver {
+            // REGISTERs are sent over UDP only
+            Some(resolver) => match resolver
+                .resolve(&format!("{};transport=udp", registrar))
+                .await
+            {
+                Ok(targets) => {
+                    let target = targets.into_iter().next();
+                    (target.as_ref().map(|target| target.addr), target)
+                }
+                Err(e) => {
+                    debug!("Cannot resolve {}: {}", registrar, e);
+                    (None, None)
+                }
+            },
+            None => (resolve(&host, port).await, None),
+        };
+
+        let request = {
+            let mut bindings = self.bindings.lock().unwrap();
+            let binding = match bindings.get_mut(&trunk_id) {
+                Some(binding) => binding,
+                None => return,
+            };
+            let destination = match destination {
+                Some(destination) => destination,
+                None => {
+                    warn!(
+                        "Cannot resolve registrar {} of trunk {}",
+                        host, binding.trunk.name
+                    );
+                    if !unregister {
+                        self.fail(binding, None, now);
+                    }
+                    return;
+                }
+            };
+            binding.unregistering = unregister;
+            binding.target = target;
+            let request = self.build_register(binding, destination);
+            binding.pending = Some((binding.cseq, now));
+            if !matches!(binding.state, RegistrationState::Registered { .. }) {
+                binding.state = RegistrationState::Registering;
+            }
+            OutgoingMessage {
+                data: Bytes::from(request),
+                destination,
+                protocol: TransportProtocol::Udp,
+            }
+        };
+
+        debug!("Sending REGISTER to {}", request.destination);
+        if let Err(e) = self.outbound.try_send(request) {
+            warn!("Failed to queue REGISTER to {}: {}", host, e);
+        }
+    }
+
+    fn build_register(&self, binding: &mut Binding, destination: SocketAddr) -> Vec<u8> {
+        let sent_by = match &self.advertiser {
+            Some(advertiser) => {
+                advertiser.advertise_host_port(&self.local_addr, Some(destination.ip()))
+            }
+            None => self.local_addr.clone(),
+        };
+        let user = binding.trunk.username.clone().unwrap_or_default();
+        binding.contact = format!("sip:{}@{}", user, sent_by);
+        binding.cseq += 1;
+        let uri = binding.registrar_uri();
+        let expires = if binding.unregistering {
+            0
+        } else {
+            binding.expires
+        };
+
+        let authorization = match &binding.challenge {
+            Some((challenge, proxy)) => {
+                binding.nonce_count += 1;
+                let auth_user = binding.trunk.auth_username.as_deref().unwrap_or(&user);
+                let password = binding.trunk.password.as_ref().map(SecretBox::expose).unwrap_or_default();
+                format!(
+                    "{}: {}\r\n",
+                    if *proxy {
+                        "Proxy-Authorization"
+                    } else {
+                        "Authorization"
+                    },
+                    challenge.authorize(auth_user, password, "REGISTER", &uri, binding.nonce_count)
+                )
+            }
+            None => String::new(),
+        };
+        binding.sent_credentials = !authorization.is_empty();
+
+        format!(
+            "REGISTER {uri} SIP/2.0\r\n\
+             Via: SIP/2.0/UDP {sent_by};rport;branch=z9hG4bK{branch}\r\n\
+             Max-Forwards: {max_forwards}\r\n\
+             From: <sip:{user}@{domain}>;tag={from_tag}\r\n\
+             To: <sip:{user}@{domain}>\r\n\
+             Call-ID: {call_id}\r\n\
+             CSeq: {cseq} REGISTER\r\n\
+             Contact: <{contact}>\r\n\
+             Expires: {expires}\r\n\
+             {authorization}\
+             Content-Length: 0\r\n\
+             \r\n",
+            uri = uri,
+            sent_by = sent_by,
+            branch = Uuid::new_v4().simple(),
+            max_forwards = DEFAULT_MAX_FORWARDS,
+            user = user,
+            domain = binding.trunk.sip_server,
+            from_tag = binding.from_tag,
+            call_id = binding.call_id,
+            cseq = binding.cseq,
+            contact = binding.contact,
+            expires = expires,
+            authorization = authorization,
+        )
+        .into_bytes()
+    }
+
+    /// How long a REGISTER to `trunk` waits for its final response
+    fn response_timeout(&self, trunk: &SipTrunk) -> Duration {
+        if let Some(secs) = self.policy.response_timeout_secs {
+            return Duration::seconds(secs as i64);
+        }
+        // REGISTERs go out over UDP
+        let timers = self.timers.timers_for(TransportProtocol::Udp, Some(&trunk.name));
+        let timer_f = TimerType::TimerF.default_duration(&timers, false);
+        Duration::from_std(timer_f).unwrap_or_else(|_| Duration::seconds(32))
+    }
+
+    fn local_domain(&self) -> &str {
+        self.local_addr
+            .rsplit_once(':')
+            .map_or(self.local_addr.as_str(), |(host, _)| host)
+    }
+}
+
+async fn resolve(host: &str, port: u16) -> Option<SocketAddr> {
+    tokio::net::lookup_host((host, port)).await.ok()?.next()
+}
+
+/// Expiry granted to `contact`: its Contact `expires` parameter, else the
+/// Expires header
+fn granted_expires(response: &SipResponse, contact: &str) -> Option<u32> {
+    let from_contact = response
+        .headers()
+        .iter()
+        .filter_map(|h| {
+            let line = h.to_string();
+            let (name, value) = line.split_once(':')?;
+            (name.trim().eq_ignore_ascii_case("Contact") || name.trim() == "m")
+                .then(|| value.trim().to_string())
+        })
+        .flat_map(|value| value.split(',').map(str::to_string).collect::<Vec<_>>())
+        .find(|value| uri_from_header(value) == contact)
+        .and_then(|value| {
+            value.split(';').skip(1).find_map(|param| {
+                let (name, expires) = param.split_once('=')?;
+                name.trim()
+                    .eq_ignore_ascii_case("expires")
+                    .then(|| expires.trim().parse().ok())?
+            })
+        });
+    from_contact.or_else(|| response_header(response, "Expires").and_then(|v| v.parse().ok()))
+}
+
+fn response_header(response: &SipResponse, name: &str) -> Option<String> {
+    response.headers().iter().find_map(|h| {
+        let line = h.to_string();
+        let (header, value) = line.split_once(':')?;
+        header
+            .trim()
+            .eq_ignore_ascii_case(name)
+            .then(|| value.trim().to_string())
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::domain::sip_trunk::TrunkType;
+    use crate::infrastructure::protocols::sip::auth::DigestAuth;
+    use crate::infrastructure::protocols::sip::builder::ResponseBuilder;
+    use crate::infrastructure::protocols::sip::message::SipRequest;
+    use rsip::Header;
+
+    fn trunk() -> SipTrunk {
+        let mut trunk = SipTrunk::new(
+            "upstream".to_string(),
+            "Provider".to_string(),
+            TrunkType::Register,
+        )
+        .with_server("127.0.0.1".to_string(), 5070)
+        .with_credentials("pbx".to_string(), "secret".to_string());
+        trunk.register_expiry = 300;
+        trunk
+    }
+
+    fn setup() -> (OutboundRegistration, mpsc::Receiver<OutgoingMessage>, Uuid) {
+        let (tx, rx) = mpsc::channel(16);
+        let registration = OutboundRegistration::new(tx, "192.0.2.1:5060".to_string()).with_policy(
+            OutboundRegistrationPolicy {
+                retry_jitter: 0.0,
+                ..Default::default()
+            },
+        );
+        let trunk = trunk();
+        let trunk_id = trunk.id;
+        assert!(registration.add_trunk(trunk));
+        (registration, rx, trunk_id)
+    }
+
+    #[test]
+    fn test_register_timeout_follows_trunk_timers() {
+        let (tx, _rx) = mpsc::channel(16);
+        let mut timers = SipTimerConfig::default();
+        timers.trunks.insert(
+            "upstream".to_string(),
+            super::super::transaction::SipTimerOverrides {
+                t1_ms: Some(1000),
+                ..Default::default()
+            },
+        );
+        let registration = OutboundRegistration::new(tx, "192.0.2.1:5060".to_string())
+            .with_timer_config(timers);
+        // Timer F: 64*T1
+        assert_eq!(registration.response_timeout(&trunk()), Duration::seconds(64));
+
+        let registration = registration.with_policy(OutboundRegistrationPolicy {
+            response_timeout_secs: Some(10),
+            ..Default::default()
+        });
+        assert_eq!(registration.response_timeout(&trunk()), Duration::seconds(10));
+    }
+
+    /// Upstream registrar answering with a scripted status
+    fn answer(
+        sent: &OutgoingMessage,
+        status: u16,
+        headers: &[(&str, String)],
+    ) -> (SipRequest, SipResponse) {
+        let request = SipRequest::parse(&sent.data).unwrap();
+        let mut builder = ResponseBuilder::new(status);
+        for (name, value) in headers {
+            builder = builder.header(Header::Other(name.to_string(), value.clone()));
+        }
+        let response = builder.build_for_request(&request).unwrap();
+        (request, response)
+    }
+
+    fn header(request: &SipRequest, name: &str) -> Option<String> {
+        request.headers().iter().find_map(|h| {
+            let line = h.to_string();
+            let (header, value) = line.split_once(':')?;
+            header
+                .trim()
+                .eq_ignore_ascii_case(name)
+                .then(|| value.trim().to_string())
+        })
+    }
+
+    #[tokio::test]
+    async fn test_challenge_round_trip_and_refresh() {
+        let (registration, mut rx, trunk_id) = setup();
+        let upstream = DigestAuth::new("provider.example");
+        upstream.add_user("pbx", "secret").await;
+
+        let start = Utc::now();
+        registration.poll_at(start).await;
+        let first = rx.try_recv().unwrap();
+        assert_eq!(first.destination, "127.0.0.1:5070".parse().unwrap());
+        let challenge = upstream.create_challenge().await;
+        let (request, response) = answer(
+            &first,
+            401,
+            &[("WWW-Authenticate", challenge.to_header_value())],
+        );
+        assert_eq!(
+            header(&request, "Contact").as_deref(),
+            Some("<sip:pbx@192.0.2.1:5060>")
+        );
+        assert!(header(&request, "Authorization").is_none());
+        registration.handle_response(&response).await;
+
+        // Sent again with credentials the upstream registrar accepts
+        let second = rx.try_recv().unwrap();
+        let (request, response) = answer(
+            &second,
+            200,
+            &[(
+                "Contact",
+                "<sip:pbx@192.0.2.1:5060>;expires=120".to_string(),
+            )],
+        );
+        assert_eq!(
+            request.call_id(),
+            SipRequest::parse(&first.data).unwrap().call_id()
+        );
+        assert_eq!(request.cseq(), Some(2));
+        assert_eq!(
+            upstream.verify_request(&request, "REGISTER").await.unwrap(),
+            "pbx"
+        );
+        registration.handle_response(&response).await;
+
+        let status = registration.status(trunk_id).unwrap();
+        let refresh_at = match status.state {
+            RegistrationState::Registered {
+                expires_at,
+                refresh_at,
+            } => {
+                assert!((expires_at - start).num_seconds() >= 119);
+                refresh_at
+            }
+            other => panic!("unexpected state {:?}", other),
+        };
+        assert!(status.last_registration.is_some());
+
+        // Refreshed after 80% of the granted 120s, not before
+        registration
+            .poll_at(refresh_at - Duration::seconds(1))
+            .await;
+        assert!(rx.try_recv().is_err());
+        registration.poll_at(refresh_at).await;
+        let refresh = SipRequest::parse(&rx.try_recv().unwrap().data).unwrap();
+        assert_eq!(refresh.cseq(), Some(3));
+        assert!(header(&refresh, "Authorization").is_some());
+    }
+
+    #[tokio::test]
+    async fn test_interval_too_brief_uses_min_expires() {
+        let (registration, mut rx, _) = setup();
+
+        registration.poll_at(Utc::now()).await;
+        let first = rx.try_recv().unwrap();
+        let (request, response) = answer(&first, 423, &[("Min-Expires", "1800".to_string())]);
+        assert_eq!(header(&request, "Expires").as_deref(), Some("300"));
+        registration.handle_response(&response).await;
+
+        let retry = SipRequest::parse(&rx.try_recv().unwrap().data).unwrap();
+        assert_eq!(header(&retry, "Expires").as_deref(), Some("1800"));
+    }
+
+    #[tokio::test]
+    async fn test_backoff_and_refused_credentials() {
+        let (registration, mut rx, trunk_id) = setup();
+
+        let start = Utc::now();
+        registration.poll_at(start).await;
+        let (_, response) = answer(&rx.try_recv().unwrap(), 503, &[]);
+        registration.handle_response(&response).await;
+        // A retransmission of the same response changes nothing
+        registration.handle_response(&response).await;
+        match registration.status(trunk_id).unwrap().state {
+            RegistrationState::Retrying {
+                last_status,
+                failures,
+                next_attempt_at,
+            } => {
+                assert_eq!(last_status, Some(503));
+                assert_eq!(failures, 1);
+                assert!((next_attempt_at - start).num_seconds() >= 29);
+            }
+            other => panic!("unexpected state {:?}", other),
+        }
+        registration.poll_at(start + Duration::seconds(10)).await;
+        assert!(rx.try_recv().is_err());
+
+        // Challenged again after answering a challenge: credentials refused
+        registration.poll_at(start + Duration::seconds(31)).await;
+        let challenge = AuthChallenge::new("provider.example").to_header_value();
+        let (_, response) = answer(
+            &rx.try_recv().unwrap(),
+            401,
+            &[("WWW-Authenticate", challenge.clone())],
+        );
+        registration.handle_response(&response).await;
+        let (_, response) = answer(
+            &rx.try_recv().unwrap(),
+            401,
+            &[("WWW-Authenticate", challenge)],
+        );
+        registration.handle_response(&response).await;
+        assert_eq!(
+            registration.status(trunk_id).unwrap().state,
+            RegistrationState::Failed { last_status: 401 }
+        );
+        registration.poll_at(start + Duration::seconds(3600)).await;
+        assert!(rx.try_recv().is_err());
+    }
+
+    #[tokio::test]
+    async fn test_unregister_on_shutdown() {
+        let (registration, mut rx, trunk_id) = setup();
+        let registration = Arc::new(registration);
+
+        registration.poll_at(Utc::now()).await;
+        let (_, response) = answer(
+            &rx.try_recv().unwrap(),
+            200,
+            &[("Expires", "300".to_string())],
+        );
+        registration.handle_response(&response).await;
+
+        let shutdown = {
+            let registration = registration.clone();
+            tokio::spawn(async move {
+                registration
+                    .unregister_all(std::time::Duration::from_secs(2))
+                    .await
+            })
+        };
+        let removal = loop {
+            if let Ok(removal) = rx.try_recv() {
+                break removal;
+            }
+            tokio::time::sleep(std::time::Duration::from_millis(5)).await;
+        };
+        let (request, response) = answer(&removal, 200, &[]);
+        assert_eq!(header(&request, "Expires").as_deref(), Some("0"));
+        registration.handle_response(&response).await;
+        shutdown.await.unwrap();
+
+        assert_eq!(
+            registration.status(trunk_id).unwrap().state,
+            RegistrationState::Unregistered
+        );
+    }
+
+    #[tokio::test]
+    async fn test_unanswered_registrar_fails_over_to_next_srv_target() {
+        use crate::infrastructure::protocols::sip::resolver::SipResolverConfig;
+        use crate::test_support::StaticDns;
+
+        let dns = StaticDns::new()
+            .srv(
+                "_sip._udp.sip.carrier.example",
+                &[
+                    (10, 0, 5060, "a.carrier.example"),
+                    (20, 0, 5062, "b.carrier.example"),
+                ],
+            )
+            .host("a.carrier.example", "192.0.2.10")
+            .host("b.carrier.example", "192.0.2.20");
+        let resolver = Arc::new(SipResolver::new(SipResolverConfig::default(), Arc::new(dns)));
+        let (tx, mut rx) = mpsc::channel(16);
+        let registration = OutboundRegistration::new(tx, "192.0.2.1:5060".to_string())
+            .with_policy(OutboundRegistrationPolicy {
+                retry_jitter: 0.0,
+                ..Default::default()
+            })
+            .with_resolver(resolver);
+        let trunk = trunk().with_server("sip.carrier.example".to_string(), 5060);
+        let trunk_id = trunk.id;
+        assert!(registration.add_trunk(trunk));
+
+        let start = Utc::now();
+        registration.poll_at(start).await;
+        assert_eq!(
+            rx.try_recv().unwrap().destination,
+            "192.0.2.10:5060".parse().unwrap()
+        );
+
+        // No answer: the retry goes to the next target
+        registration.poll_at(start + Duration::seconds(32)).await;
+        registration.poll_at(start + Duration::seconds(63)).await;
+        let retry = rx.try_recv().unwrap();
+        assert_eq!(retry.destination, "192.0.2.20:5062".parse().unwrap());
+
+        let (_, response) = answer(&retry, 200, &[("Expires", "300".to_string())]);
+        registration.handle_response(&response).await;
+        let status = registration.status(trunk_id).unwrap();
+        assert!(matches!(status.state, RegistrationState::Registered { .. }));
+        assert_eq!(
+            status.registrar_address.as_deref(),
+            Some("UDP 192.0.2.20:5062 (b.carrier.example)")
+        );
+    }
+
+    #[test]
+    fn test_retry_delay() {
+        let policy = OutboundRegistrationPolicy {
+            retry_initial_secs: 30,
+            retry_max_secs: 200,
+            ..Default::default()
+        };
+        assert_eq!(policy.retry_delay(1), Duration::seconds(30));
+        assert_eq!(policy.retry_delay(3), Duration::seconds(120));
+        assert_eq!(policy.retry_delay(9), Duration::seconds(200));
+        assert_eq!(policy.refresh_after(3600), Duration::seconds(2880));
+    }
+}
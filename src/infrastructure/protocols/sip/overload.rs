@@ -0,0 +1,491 @@
+//! Overload control on the SIP ingress path
+//!
+//! [`OverloadMonitor`] is fed samples of the event-loop lag, the server
+//! transactions in progress, the media port pool usage and the CDR write
+//! queue. The most loaded measure, as a fraction of its limit, sets the
+//! overload level, which escalates step by step:
+//!
+//! - shedding (from `shed_at`): optional work is skipped, i.e. number
+//!   portability lookups and per-call debug logging
+//! - rejecting (from `reject_at`): `reject_percent` of new INVITEs are
+//!   answered 503 with a jittered Retry-After
+//! - critical (from `critical_at`): every new dialog-forming request
+//!   (INVITE, SUBSCRIBE or REFER outside a dialog) is answered 503
+//!
+//! REGISTER, OPTIONS and requests within a dialog (re-INVITE, BYE, ACK,
+//! CANCEL...) are always served, so calls in progress are never touched.
+//! A level is only left once the load is `hysteresis` below its threshold.
+
+use super::message::SipRequest;
+use chrono::{DateTime, Utc};
+use metrics::{counter, gauge};
+use rand::Rng;
+use rsip::Method;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::task::JoinHandle;
+use tracing::{info, warn};
+
+/// How much new work the server takes
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum OverloadLevel {
+    #[default]
+    Normal,
+    /// Optional work is skipped
+    Shedding,
+    /// A share of new INVITEs is refused
+    Rejecting,
+    /// Every new dialog is refused
+    Critical,
+}
+
+impl fmt::Display for OverloadLevel {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            OverloadLevel::Normal => "normal",
+            OverloadLevel::Shedding => "shedding",
+            OverloadLevel::Rejecting => "rejecting",
+            OverloadLevel::Critical => "critical",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+/// Limits, level thresholds and the 503 answers
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct OverloadConfig {
+    pub enabled: bool,
+    /// How often the inputs are sampled
+    pub sample_interval_ms: u64,
+    /// Event-loop lag counted as full load
+    pub max_event_loop_lag_ms: u64,
+    /// Server transactions in progress counted as full load
+    pub max_transactions: usize,
+    /// Queued CDR writes counted as full load
+    pub max_cdr_queue: usize,
+    /// Load (fraction of a limit) from which optional work is skipped
+    pub shed_at: f64,
+    /// Load from which `reject_percent` of new INVITEs are refused
+    pub reject_at: f64,
+    /// Load from which every new dialog is refused
+    pub critical_at: f64,
+    /// How far below its threshold the load must drop to leave a level
+    pub hysteresis: f64,
+    /// Share of new INVITEs refused while rejecting, in percent
+    pub reject_percent: u32,
+    /// Retry-After of refused requests, in seconds
+    pub retry_after_secs: u32,
+    /// Random seconds added to Retry-After, so refused callers do not all
+    /// come back at once
+    pub retry_after_jitter_secs: u32,
+}
+
+impl Default for OverloadConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            sample_interval_ms: 500,
+            max_event_loop_lag_ms: 250,
+            max_transactions: 10_000,
+            max_cdr_queue: 10_000,
+            shed_at: 0.7,
+            reject_at: 0.85,
+            critical_at: 1.0,
+            hysteresis: 0.1,
+            reject_percent: 50,
+            retry_after_secs: 10,
+            retry_after_jitter_secs: 10,
+        }
+    }
+}
+
+impl OverloadConfig {
+    pub fn validate(&self) -> Result<(), String> {
+        if !(self.shed_at > 0.0 && self.shed_at <= self.reject_at && self.reject_at <= self.critical_at) {
+            return Err(format!(
+                "shed_at {}, reject_at {} and critical_at {} must satisfy 0 < shed_at <= reject_at <= critical_at",
+                self.shed_at, self.reject_at, self.critical_at
+            ));
+        }
+        if !(self.hysteresis >= 0.0 && self.hysteresis < self.shed_at) {
+            return Err(format!(
+                "hysteresis {} must be between 0 and shed_at {}",
+                self.hysteresis, self.shed_at
+            ));
+        }
+        if !(1..=100).contains(&self.reject_percent) {
+            return Err(format!("reject_percent {} is outside 1-100", self.reject_percent));
+        }
+        if self.sample_interval_ms == 0
+            || self.max_event_loop_lag_ms == 0
+            || self.max_transactions == 0
+            || self.max_cdr_queue == 0
+        {
+            return Err("sample_interval_ms and the limits must be positive".to_string());
+        }
+        Ok(())
+    }
+}
+
+/// Inputs of one sampling round
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+pub struct OverloadSample {
+    /// How late a timer of the sampling task fired, in milliseconds
+    pub event_loop_lag_ms: u64,
+    /// Server transactions in progress
+    pub transactions: usize,
+    /// Fraction of the media port pool in use
+    pub port_usage: f64,
+    /// CDR writes waiting for the database
+    pub cdr_queue: usize,
+}
+
+/// Current level and what caused it
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct OverloadStatus {
+    pub level: OverloadLevel,
+    /// Load of the most loaded measure, as a fraction of its limit
+    pub load: f64,
+    /// Most loaded measure, e.g. `transactions 9000/10000`
+    pub reason: Option<String>,
+    /// When the current level was entered
+    pub since: Option<DateTime<Utc>>,
+    pub sample: OverloadSample,
+}
+
+#[derive(Debug, Default)]
+struct MonitorState {
+    level: OverloadLevel,
+    load: f64,
+    reason: Option<String>,
+    since: Option<DateTime<Utc>>,
+    sample: OverloadSample,
+    /// Rejection credit in percent; an INVITE is refused each time it
+    /// reaches 100, so exactly `reject_percent` of them are
+    credit: u32,
+}
+
+/// Samples the load and decides which new requests are refused
+pub struct OverloadMonitor {
+    config: OverloadConfig,
+    state: Mutex<MonitorState>,
+}
+
+impl OverloadMonitor {
+    pub fn new(config: OverloadConfig) -> Self {
+        Self {
+            config,
+            state: Mutex::new(MonitorState::default()),
+        }
+    }
+
+    pub fn config(&self) -> &OverloadConfig {
+        &self.config
+    }
+
+    pub fn level(&self) -> OverloadLevel {
+        self.state.lock().unwrap().level
+    }
+
+    /// Whether optional work should be skipped
+    pub fn is_shedding(&self) -> bool {
+        self.level() >= OverloadLevel::Shedding
+    }
+
+    pub fn status(&self) -> OverloadStatus {
+        let state = self.state.lock().unwrap();
+        OverloadStatus {
+            level: state.level,
+            load: state.load,
+            reason: state.reason.clone(),
+            since: state.since,
+            sample: state.sample,
+        }
+    }
+
+    /// Take a sample and move to the level it calls for
+    pub fn observe(&self, sample: OverloadSample) -> OverloadLevel {
+        let (load, reason) = self.peak_load(&sample);
+        let mut state = self.state.lock().unwrap();
+        state.sample = sample;
+        state.load = load;
+        state.reason = Some(reason);
+
+        let level = if self.config.enabled {
+            self.level_for(load, state.level)
+        } else {
+            OverloadLevel::Normal
+        };
+        if level != state.level {
+            let reason = state.reason.as_deref().unwrap_or_default();
+            if level > state.level {
+                warn!("Overload level {} -> {}: {}", state.level, level, reason);
+            } else {
+                info!("Overload level {} -> {}: {}", state.level, level, reason);
+            }
+            state.level = level;
+            state.since = (level != OverloadLevel::Normal).then(Utc::now);
+            state.credit = 0;
+            gauge!("sip_overload_level").set(level as u8 as f64);
+        }
+        level
+    }
+
+    /// Sample every `sample_interval_ms`; `read` returns the current
+    /// transactions, port usage and CDR queue, the event-loop lag is how
+    /// late the sampling timer fired
+    pub fn spawn_sampler<F>(self: Arc<Self>, read: F) -> JoinHandle<()>
+    where
+        F: Fn() -> OverloadSample + Send + 'static,
+    {
+        let interval = Duration::from_millis(self.config.sample_interval_ms.max(1));
+        tokio::spawn(async move {
+            loop {
+                let started = Instant::now();
+                tokio::time::sleep(interval).await;
+                let lag = started.elapsed().saturating_sub(interval);
+                self.observe(OverloadSample {
+                    event_loop_lag_ms: lag.as_millis() as u64,
+                    ..read()
+                });
+            }
+        })
+    }
+
+    /// Retry-After seconds when `request` is refused, `None` to serve it
+    pub fn admit(&self, request: &SipRequest) -> Option<u32> {
+        // The request's own method, so no method escapes admission
+        let method = &request.inner.method;
+        if !Self::forms_dialog(method, request) {
+            return None;
+        }
+
+        let refused = {
+            let mut state = self.state.lock().unwrap();
+            match state.level {
+                OverloadLevel::Normal | OverloadLevel::Shedding => false,
+                OverloadLevel::Rejecting if *method == Method::Invite => {
+                    state.credit += self.config.reject_percent;
+                    if state.credit >= 100 {
+                        state.credit -= 100;
+                        true
+                    } else {
+                        false
+                    }
+                }
+                OverloadLevel::Rejecting => false,
+                OverloadLevel::Critical => true,
+            }
+        };
+        if !refused {
+            return None;
+        }
+        counter!("sip_overload_rejected_total", "method" => method.to_string()).increment(1);
+        let jitter = match self.config.retry_after_jitter_secs {
+            0 => 0,
+            jitter => rand::thread_rng().gen_range(0..=jitter),
+        };
+        Some(self.config.retry_after_secs + jitter)
+    }
+
+    /// New INVITE, SUBSCRIBE or REFER, i.e. one without a To tag
+    fn forms_dialog(method: &Method, request: &SipRequest) -> bool {
+        matches!(method, Method::Invite | Method::Subscribe | Method::Refer)
+            && request.to_tag().is_none()
+    }
+
+    /// Level for `load`; the levels up to `current` are held until the
+    /// load drops `hysteresis` below their threshold
+    fn level_for(&self, load: f64, current: OverloadLevel) -> OverloadLevel {
+        let thresholds = [
+            (OverloadLevel::Critical, self.config.critical_at),
+            (OverloadLevel::Rejecting, self.config.reject_at),
+            (OverloadLevel::Shedding, self.config.shed_at),
+        ];
+        for (level, at) in thresholds {
+            let enter = if level <= current {
+                at - self.config.hysteresis
+            } else {
+                at
+            };
+            if load >= enter {
+                return level;
+            }
+        }
+        OverloadLevel::Normal
+    }
+
+    /// The most loaded measure as (fraction of its limit, description)
+    fn peak_load(&self, sample: &OverloadSample) -> (f64, String) {
+        let config = &self.config;
+        let loads = [
+            (
+                sample.event_loop_lag_ms as f64 / config.max_event_loop_lag_ms.max(1) as f64,
+                format!(
+                    "event loop lag {}/{} ms",
+                    sample.event_loop_lag_ms, config.max_event_loop_lag_ms
+                ),
+            ),
+            (
+                sample.transactions as f64 / config.max_transactions.max(1) as f64,
+                format!("transactions {}/{}", sample.transactions, config.max_transactions),
+            ),
+            (
+                sample.port_usage,
+                format!("media ports {:.0}% in use", sample.port_usage * 100.0),
+            ),
+            (
+                sample.cdr_queue as f64 / config.max_cdr_queue.max(1) as f64,
+                format!("CDR queue {}/{}", sample.cdr_queue, config.max_cdr_queue),
+            ),
+        ];
+        loads
+            .into_iter()
+            .max_by(|a, b| a.0.total_cmp(&b.0))
+            .unwrap_or_default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn request(method: &str, call_id: &str, to_tag: Option<&str>) -> SipRequest {
+        let to_tag = to_tag.map(|tag| format!(";tag={}", tag)).unwrap_or_default();
+        SipRequest::parse(
+            format!(
+                "{method} sip:bob@example.com SIP/2.0\r\n\
+                 Via: SIP/2.0/UDP 10.0.0.1:5060;branch=z9hG4bK-{call_id}\r\n\
+                 From: <sip:alice@example.com>;tag=a1\r\n\
+                 To: <sip:bob@example.com>{to_tag}\r\n\
+                 Call-ID: {call_id}\r\n\
+                 CSeq: 1 {method}\r\n\
+                 Content-Length: 0\r\n\r\n"
+            )
+            .as_bytes(),
+        )
+        .unwrap()
+    }
+
+    /// Sample with `transactions` out of the default 10000
+    fn transactions(transactions: usize) -> OverloadSample {
+        OverloadSample {
+            transactions,
+            ..Default::default()
+        }
+    }
+
+    fn monitor() -> OverloadMonitor {
+        OverloadMonitor::new(OverloadConfig {
+            reject_percent: 30,
+            retry_after_secs: 5,
+            retry_after_jitter_secs: 3,
+            ..Default::default()
+        })
+    }
+
+    /// Share of `count` new INVITEs refused, checking their Retry-After
+    fn refused_invites(monitor: &OverloadMonitor, count: usize) -> usize {
+        (0..count)
+            .filter_map(|i| monitor.admit(&request("INVITE", &format!("new-{}", i), None)))
+            .inspect(|retry_after| assert!((5..=8).contains(retry_after)))
+            .count()
+    }
+
+    /// REGISTER and requests within a dialog are served at every level
+    fn assert_protected(monitor: &OverloadMonitor) {
+        assert_eq!(monitor.admit(&request("REGISTER", "reg", None)), None);
+        assert_eq!(monitor.admit(&request("OPTIONS", "ping", None)), None);
+        assert_eq!(monitor.admit(&request("BYE", "call", Some("b1"))), None);
+        assert_eq!(monitor.admit(&request("INVITE", "call", Some("b1"))), None);
+        assert_eq!(monitor.admit(&request("ACK", "call", Some("b1"))), None);
+    }
+
+    #[test]
+    fn test_levels_and_rejection() {
+        let monitor = monitor();
+        assert_eq!(monitor.observe(transactions(5000)), OverloadLevel::Normal);
+        assert_eq!(refused_invites(&monitor, 100), 0);
+        assert_protected(&monitor);
+
+        // Shedding skips optional work but refuses nothing
+        assert_eq!(monitor.observe(transactions(7500)), OverloadLevel::Shedding);
+        assert!(monitor.is_shedding());
+        assert_eq!(refused_invites(&monitor, 100), 0);
+        assert_protected(&monitor);
+
+        // Rejecting refuses 30% of new INVITEs, not new SUBSCRIBEs
+        assert_eq!(monitor.observe(transactions(9000)), OverloadLevel::Rejecting);
+        assert_eq!(refused_invites(&monitor, 100), 30);
+        assert_eq!(refused_invites(&monitor, 10), 3);
+        assert_eq!(monitor.admit(&request("SUBSCRIBE", "sub", None)), None);
+        assert_protected(&monitor);
+
+        // Critical refuses every new dialog
+        let sample = OverloadSample {
+            event_loop_lag_ms: 300,
+            ..transactions(9000)
+        };
+        assert_eq!(monitor.observe(sample), OverloadLevel::Critical);
+        assert_eq!(refused_invites(&monitor, 100), 100);
+        assert!(monitor.admit(&request("SUBSCRIBE", "sub", None)).is_some());
+        assert!(monitor.admit(&request("REFER", "refer", None)).is_some());
+        assert_protected(&monitor);
+
+        let status = monitor.status();
+        assert_eq!(status.level, OverloadLevel::Critical);
+        assert_eq!(status.reason.as_deref(), Some("event loop lag 300/250 ms"));
+        assert!(status.since.is_some());
+    }
+
+    #[test]
+    fn test_recovery_hysteresis() {
+        let monitor = monitor();
+        let ports = |port_usage| OverloadSample {
+            port_usage,
+            ..Default::default()
+        };
+        assert_eq!(monitor.observe(ports(1.0)), OverloadLevel::Critical);
+
+        // Just below a threshold the level is held
+        assert_eq!(monitor.observe(ports(0.95)), OverloadLevel::Critical);
+        assert_eq!(monitor.observe(ports(0.89)), OverloadLevel::Rejecting);
+        assert_eq!(monitor.observe(ports(0.8)), OverloadLevel::Rejecting);
+        assert_eq!(monitor.observe(ports(0.74)), OverloadLevel::Shedding);
+        assert_eq!(monitor.observe(ports(0.62)), OverloadLevel::Shedding);
+        assert_eq!(monitor.observe(ports(0.59)), OverloadLevel::Normal);
+        assert!(monitor.status().since.is_none());
+
+        // Going up again needs the full threshold
+        assert_eq!(monitor.observe(ports(0.65)), OverloadLevel::Normal);
+        assert_eq!(monitor.observe(ports(0.7)), OverloadLevel::Shedding);
+    }
+
+    #[test]
+    fn test_disabled_and_validate() {
+        let monitor = OverloadMonitor::new(OverloadConfig {
+            enabled: false,
+            ..Default::default()
+        });
+        assert_eq!(monitor.observe(transactions(20_000)), OverloadLevel::Normal);
+        assert_eq!(monitor.admit(&request("INVITE", "new", None)), None);
+        // The load is still reported
+        assert_eq!(monitor.status().load, 2.0);
+
+        assert!(OverloadConfig::default().validate().is_ok());
+        let config = OverloadConfig {
+            reject_at: 0.6,
+            ..Default::default()
+        };
+        assert!(config.validate().is_err());
+        let config = OverloadConfig {
+            reject_percent: 0,
+            ..Default::default()
+        };
+        assert!(config.validate().is_err());
+    }
+}
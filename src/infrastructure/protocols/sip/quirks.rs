@@ -0,0 +1,444 @@
+//! Per-device interop workarounds
+//!
+//! Devices are matched on their User-Agent header against configured
+//! rules; the first matching rule's toggles are applied to requests as they
+//! arrive and to the responses sent back to that device. Every applied
+//! workaround is counted in `sip_quirks_applied_total` and logged at debug
+//! level with the rule that matched.
+
+use super::address::{format_host, parse_host_port, uri_from_header};
+use super::message::{SipRequest, SipResponse};
+use metrics::counter;
+use regex::Regex;
+use rsip::headers::UntypedHeader;
+use rsip::Header;
+use serde::{Deserialize, Serialize};
+use std::net::IpAddr;
+use std::sync::OnceLock;
+use tracing::{debug, warn};
+
+/// Max-Forwards inserted by `tolerate_missing_max_forwards` (RFC 3261 8.1.1.6)
+pub const DEFAULT_MAX_FORWARDS: u32 = 70;
+
+/// A workaround that can be switched on for a device
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Quirk {
+    /// Keep only the first Contact header in responses
+    SuppressMultipleContacts,
+    /// Add an Allow header to every response
+    AlwaysIncludeAllow,
+    /// Insert Max-Forwards into requests that lack it
+    TolerateMissingMaxForwards,
+    /// Bracket IPv6 hosts sent unbracketed in Contact
+    RewriteBrokenIpv6Contact,
+    /// Lift a Contact `expires` parameter into an Expires header
+    ExpiresFromContact,
+}
+
+impl Quirk {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Quirk::SuppressMultipleContacts => "suppress_multiple_contacts",
+            Quirk::AlwaysIncludeAllow => "always_include_allow",
+            Quirk::TolerateMissingMaxForwards => "tolerate_missing_max_forwards",
+            Quirk::RewriteBrokenIpv6Contact => "rewrite_broken_ipv6_contact",
+            Quirk::ExpiresFromContact => "expires_from_contact",
+        }
+    }
+}
+
+/// Workarounds for devices whose User-Agent matches `user_agent`
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct QuirkRule {
+    /// Rule name used in logs and metrics
+    pub name: String,
+    /// Regular expression matched against the User-Agent header
+    pub user_agent: String,
+    pub suppress_multiple_contacts: bool,
+    pub always_include_allow: bool,
+    pub tolerate_missing_max_forwards: bool,
+    pub rewrite_broken_ipv6_contact: bool,
+    pub expires_from_contact: bool,
+}
+
+impl QuirkRule {
+    pub fn new(name: &str, user_agent: &str) -> Self {
+        Self {
+            name: name.to_string(),
+            user_agent: user_agent.to_string(),
+            ..Default::default()
+        }
+    }
+
+    /// Switch on a workaround
+    pub fn with(mut self, quirk: Quirk) -> Self {
+        match quirk {
+            Quirk::SuppressMultipleContacts => self.suppress_multiple_contacts = true,
+            Quirk::AlwaysIncludeAllow => self.always_include_allow = true,
+            Quirk::TolerateMissingMaxForwards => self.tolerate_missing_max_forwards = true,
+            Quirk::RewriteBrokenIpv6Contact => self.rewrite_broken_ipv6_contact = true,
+            Quirk::ExpiresFromContact => self.expires_from_contact = true,
+        }
+        self
+    }
+
+    /// Rules shipped for devices known to need workarounds
+    pub fn defaults() -> Vec<QuirkRule> {
+        vec![
+            // Re-registers in a loop when a 200 OK lists other devices' contacts
+            QuirkRule::new("yealink-single-contact", r"^Yealink SIP-T[0-9]")
+                .with(Quirk::SuppressMultipleContacts),
+            // Refuses to send re-INVITEs unless every response carries Allow
+            QuirkRule::new("cisco-spa-allow", r"^Cisco/SPA[0-9]")
+                .with(Quirk::AlwaysIncludeAllow),
+            // Only sends the registration interval as a Contact parameter and
+            // writes IPv6 contacts without brackets
+            QuirkRule::new("grandstream-legacy", r"^Grandstream (HT|GXP)[0-9]")
+                .with(Quirk::ExpiresFromContact)
+                .with(Quirk::RewriteBrokenIpv6Contact)
+                .with(Quirk::TolerateMissingMaxForwards),
+        ]
+    }
+}
+
+struct CompiledRule {
+    rule: QuirkRule,
+    pattern: Regex,
+}
+
+/// Registry of quirk rules, applied by the SIP server
+pub struct QuirksRegistry {
+    rules: Vec<CompiledRule>,
+    /// Allow header added by `always_include_allow`, the one OPTIONS
+    /// answers with
+    allow: OnceLock<String>,
+}
+
+impl QuirksRegistry {
+    /// Compile `rules`; rules are tried in order and the first match wins
+    pub fn new(rules: Vec<QuirkRule>) -> Result<Self, String> {
+        let rules = rules
+            .into_iter()
+            .map(|rule| {
+                let pattern = Regex::new(&rule.user_agent).map_err(|e| {
+                    format!("Invalid User-Agent pattern in quirk rule '{}': {}", rule.name, e)
+                })?;
+                Ok(CompiledRule { rule, pattern })
+            })
+            .collect::<Result<Vec<_>, String>>()?;
+        Ok(Self {
+            rules,
+            allow: OnceLock::new(),
+        })
+    }
+
+    /// Registry with the shipped rule set
+    pub fn with_defaults() -> Self {
+        Self::new(QuirkRule::defaults()).expect("default quirk rules must compile")
+    }
+
+    /// Set the Allow value once the handlers are registered; until then
+    /// `always_include_allow` has nothing to add
+    pub fn set_allow(&self, allow: String) {
+        if self.allow.set(allow).is_err() {
+            warn!("Allow header of interop quirks already set");
+        }
+    }
+
+    /// Configured rules, in match order
+    pub fn rules(&self) -> impl Iterator<Item = &QuirkRule> {
+        self.rules.iter().map(|r| &r.rule)
+    }
+
+    /// First rule matching a User-Agent
+    pub fn match_user_agent(&self, user_agent: &str) -> Option<&QuirkRule> {
+        self.rules
+            .iter()
+            .find(|r| r.pattern.is_match(user_agent))
+            .map(|r| &r.rule)
+    }
+
+    /// Normalize a request from a matching device before it is handled
+    pub fn apply_to_request(&self, request: &mut SipRequest) -> Vec<Quirk> {
+        let rule = match user_agent(request).and_then(|ua| self.match_user_agent(&ua)) {
+            Some(rule) => rule,
+            None => return Vec::new(),
+        };
+        let headers = &mut request.inner.headers;
+        let mut applied = Vec::new();
+
+        if rule.tolerate_missing_max_forwards
+            && !headers.iter().any(|h| matches!(h, Header::MaxForwards(_)))
+        {
+            headers.push(Header::MaxForwards(DEFAULT_MAX_FORWARDS.to_string().into()));
+            applied.push(Quirk::TolerateMissingMaxForwards);
+        }
+
+        if rule.rewrite_broken_ipv6_contact {
+            let mut rewritten = false;
+            for header in headers.iter_mut() {
+                if let Header::Contact(contact) = header {
+                    if let Some(fixed) = bracket_ipv6_contact(contact.value()) {
+                        *header = Header::Contact(fixed.into());
+                        rewritten = true;
+                    }
+                }
+            }
+            if rewritten {
+                applied.push(Quirk::RewriteBrokenIpv6Contact);
+            }
+        }
+
+        if rule.expires_from_contact && !headers.iter().any(|h| matches!(h, Header::Expires(_))) {
+            let expires = headers.iter().find_map(|h| match h {
+                Header::Contact(contact) => contact_expires(contact.value()),
+                _ => None,
+            });
+            if let Some(expires) = expires {
+                headers.push(Header::Expires(expires.to_string().into()));
+                applied.push(Quirk::ExpiresFromContact);
+            }
+        }
+
+        record(rule, &applied);
+        applied
+    }
+
+    /// Adjust a response for the device that sent `request`
+    pub fn apply_to_response(&self, request: &SipRequest, response: &mut SipResponse) -> Vec<Quirk> {
+        let rule = match user_agent(request).and_then(|ua| self.match_user_agent(&ua)) {
+            Some(rule) => rule,
+            None => return Vec::new(),
+        };
+        let headers = &mut response.inner.headers;
+        let mut applied = Vec::new();
+
+        if rule.suppress_multiple_contacts
+            && headers.iter().filter(|h| matches!(h, Header::Contact(_))).count() > 1
+        {
+            let mut first = true;
+            headers.retain(|h| match h {
+                Header::Contact(_) => std::mem::replace(&mut first, false),
+                _ => true,
+            });
+            applied.push(Quirk::SuppressMultipleContacts);
+        }
+
+        if let Some(allow) = self.allow.get().filter(|_| rule.always_include_allow) {
+            if !headers.iter().any(|h| matches!(h, Header::Allow(_))) {
+                headers.push(Header::Allow(allow.clone().into()));
+                applied.push(Quirk::AlwaysIncludeAllow);
+            }
+        }
+
+        record(rule, &applied);
+        applied
+    }
+}
+
+impl Default for QuirksRegistry {
+    fn default() -> Self {
+        Self::with_defaults()
+    }
+}
+
+/// User-Agent header value of a request
+fn user_agent(request: &SipRequest) -> Option<String> {
+    request.headers().iter().find_map(|h| match h {
+        Header::UserAgent(ua) => Some(ua.value().to_string()),
+        _ => None,
+    })
+}
+
+fn record(rule: &QuirkRule, applied: &[Quirk]) {
+    for quirk in applied {
+        debug!("Applied quirk {} (rule '{}')", quirk.as_str(), rule.name);
+        counter!("sip_quirks_applied_total", "rule" => rule.name.clone(), "quirk" => quirk.as_str())
+            .increment(1);
+    }
+}
+
+/// Contact value with an unbracketed IPv6 host bracketed, if it had one
+///
+/// Devices with this bug append the port after the address, so the last
+/// group is read as the port whenever the rest is still an IPv6 address.
+fn bracket_ipv6_contact(value: &str) -> Option<String> {
+    let uri = uri_from_header(value);
+    let (scheme_user, host_port) = match uri.rsplit_once('@') {
+        Some((prefix, rest)) => (format!("{}@", prefix), rest),
+        None => {
+            let (scheme, rest) = uri.split_once(':')?;
+            (format!("{}:", scheme), rest)
+        }
+    };
+    let end = host_port.find([';', '?']).unwrap_or(host_port.len());
+    let (host_port, params) = host_port.split_at(end);
+    if host_port.starts_with('[') || host_port.matches(':').count() < 2 {
+        return None;
+    }
+
+    let with_port = host_port
+        .rsplit_once(':')
+        .and_then(|(host, port)| parse_host_port(&format!("[{}]:{}", host, port), 0));
+    let fixed_host_port = match with_port {
+        Some(addr) => format!("{}:{}", format_host(addr.ip()), addr.port()),
+        None => format_host(host_port.parse::<IpAddr>().ok()?),
+    };
+    let fixed_uri = format!("{}{}{}", scheme_user, fixed_host_port, params);
+    Some(value.replacen(uri, &fixed_uri, 1))
+}
+
+/// `expires` parameter of a Contact header value
+fn contact_expires(value: &str) -> Option<u32> {
+    let params = match value.rfind('>') {
+        Some(end) => &value[end + 1..],
+        None => value.split_once(';').map(|(_, p)| p)?,
+    };
+    params.split(';').find_map(|param| {
+        let (name, value) = param.split_once('=')?;
+        if name.trim().eq_ignore_ascii_case("expires") {
+            value.trim().parse().ok()
+        } else {
+            None
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::infrastructure::protocols::sip::builder::ResponseBuilder;
+
+    fn fake_device() -> QuirkRule {
+        QuirkRule::new("fake-phone", r"^FakePhone/[0-9]")
+            .with(Quirk::SuppressMultipleContacts)
+            .with(Quirk::AlwaysIncludeAllow)
+            .with(Quirk::TolerateMissingMaxForwards)
+            .with(Quirk::RewriteBrokenIpv6Contact)
+            .with(Quirk::ExpiresFromContact)
+    }
+
+    fn register_from(user_agent: &str) -> SipRequest {
+        let request = format!(
+            "REGISTER sip:example.com SIP/2.0\r\n\
+             Via: SIP/2.0/UDP [2001:db8::7]:5062;branch=z9hG4bKq1\r\n\
+             From: <sip:alice@example.com>;tag=q1\r\n\
+             To: <sip:alice@example.com>\r\n\
+             Call-ID: quirks-1\r\n\
+             CSeq: 1 REGISTER\r\n\
+             Contact: <sip:alice@2001:db8::7:5062;transport=udp>;expires=120\r\n\
+             User-Agent: {}\r\n\
+             Content-Length: 0\r\n\r\n",
+            user_agent
+        );
+        SipRequest::parse(request.as_bytes()).unwrap()
+    }
+
+    fn two_contact_response(request: &SipRequest) -> SipResponse {
+        ResponseBuilder::ok()
+            .header(Header::Contact("<sip:alice@192.0.2.10:5060>".into()))
+            .header(Header::Contact("<sip:alice@192.0.2.11:5060>".into()))
+            .build_for_request(request)
+            .unwrap()
+    }
+
+    fn header_value(headers: &rsip::Headers, f: fn(&Header) -> Option<String>) -> Option<String> {
+        headers.iter().find_map(f)
+    }
+
+    #[test]
+    fn test_request_quirks_for_matching_device() {
+        let registry = QuirksRegistry::new(vec![fake_device()]).unwrap();
+        let mut request = register_from("FakePhone/2.1");
+
+        let applied = registry.apply_to_request(&mut request);
+        assert_eq!(
+            applied,
+            vec![
+                Quirk::TolerateMissingMaxForwards,
+                Quirk::RewriteBrokenIpv6Contact,
+                Quirk::ExpiresFromContact,
+            ]
+        );
+
+        let headers = request.headers();
+        assert_eq!(
+            header_value(headers, |h| match h {
+                Header::MaxForwards(m) => Some(m.value().to_string()),
+                _ => None,
+            }),
+            Some("70".to_string())
+        );
+        assert_eq!(
+            header_value(headers, |h| match h {
+                Header::Contact(c) => Some(c.value().to_string()),
+                _ => None,
+            }),
+            Some("<sip:alice@[2001:db8::7]:5062;transport=udp>;expires=120".to_string())
+        );
+        assert_eq!(
+            header_value(headers, |h| match h {
+                Header::Expires(e) => Some(e.value().to_string()),
+                _ => None,
+            }),
+            Some("120".to_string())
+        );
+    }
+
+    #[test]
+    fn test_response_quirks_for_matching_device() {
+        let registry = QuirksRegistry::new(vec![fake_device()]).unwrap();
+        registry.set_allow("INVITE, ACK, OPTIONS, REGISTER".to_string());
+        let request = register_from("FakePhone/2.1");
+        let mut response = two_contact_response(&request);
+
+        let applied = registry.apply_to_response(&request, &mut response);
+        assert_eq!(applied, vec![Quirk::SuppressMultipleContacts, Quirk::AlwaysIncludeAllow]);
+
+        let contacts: Vec<_> = response
+            .headers()
+            .iter()
+            .filter_map(|h| match h {
+                Header::Contact(c) => Some(c.value().to_string()),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(contacts, vec!["<sip:alice@192.0.2.10:5060>".to_string()]);
+        assert!(response
+            .headers()
+            .iter()
+            .any(|h| matches!(h, Header::Allow(a) if a.value() == "INVITE, ACK, OPTIONS, REGISTER")));
+
+        // Applying again changes nothing
+        assert!(registry.apply_to_response(&request, &mut response).is_empty());
+    }
+
+    #[test]
+    fn test_other_devices_untouched() {
+        let registry = QuirksRegistry::new(vec![fake_device()]).unwrap();
+        let mut request = register_from("OtherPhone/1.0");
+        let original = request.to_bytes();
+
+        assert!(registry.apply_to_request(&mut request).is_empty());
+        assert_eq!(request.to_bytes(), original);
+
+        let mut response = two_contact_response(&request);
+        assert!(registry.apply_to_response(&request, &mut response).is_empty());
+        assert_eq!(
+            response.headers().iter().filter(|h| matches!(h, Header::Contact(_))).count(),
+            2
+        );
+    }
+
+    #[test]
+    fn test_default_rules_and_invalid_pattern() {
+        let registry = QuirksRegistry::with_defaults();
+        let rule = registry.match_user_agent("Yealink SIP-T46S 66.86.0.15").unwrap();
+        assert!(rule.suppress_multiple_contacts);
+        assert!(registry.match_user_agent("YakYak/0.1").is_none());
+
+        let err = QuirksRegistry::new(vec![QuirkRule::new("broken", "([")]).err().unwrap();
+        assert!(err.contains("broken"));
+    }
+}
@@ -0,0 +1,616 @@
+//! 3xx redirect recursion for forwarded INVITEs
+//!
+//! When a forwarded INVITE is answered with a 3xx, the Contacts of the
+//! redirect are tried in q-value order, subject to a [`RedirectPolicy`]:
+//! a maximum number of redirects, deny patterns, and trust boundaries
+//! (a trunk may not redirect a call onto an internal target unless allowed).
+//! A redirect back to a URI that was already attempted aborts the call with
+//! 482 Loop Detected.
+
+use super::branding::brand_request;
+use super::builder::ResponseBuilder;
+use super::header_rules::{HeaderContext, HeaderRulesEngine};
+use super::hops::{forwarded_max_forwards, HopTracker};
+use super::message::{SipError, SipRequest, SipResponse};
+use super::quirks::DEFAULT_MAX_FORWARDS;
+use crate::domain::tenant_branding::TenantBranding;
+use async_trait::async_trait;
+use rsip::headers::UntypedHeader;
+use rsip::Header;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashSet, VecDeque};
+use std::sync::Arc;
+use tokio::sync::mpsc;
+use tracing::{debug, info, warn};
+
+/// Where a call or target sits relative to the PBX
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TrustZone {
+    /// Registered users and PBX services
+    Internal,
+    /// SIP trunks
+    Trunk,
+    /// Any other SIP destination
+    External,
+}
+
+/// Which redirects are followed
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct RedirectPolicy {
+    /// Maximum number of 3xx responses followed per call
+    pub max_redirects: u32,
+    /// Contacts matching any of these patterns (`*` wildcard) are skipped
+    pub deny_patterns: Vec<String>,
+    /// Domains whose URIs are internal targets
+    pub internal_domains: Vec<String>,
+    /// Let trunks redirect calls to internal targets
+    pub allow_trunk_to_internal: bool,
+}
+
+impl Default for RedirectPolicy {
+    fn default() -> Self {
+        Self {
+            max_redirects: 3,
+            deny_patterns: Vec::new(),
+            internal_domains: Vec::new(),
+            allow_trunk_to_internal: false,
+        }
+    }
+}
+
+impl RedirectPolicy {
+    /// Trust zone of a target URI
+    pub fn zone_of(&self, uri: &str) -> TrustZone {
+        let host = uri_host(uri);
+        if self
+            .internal_domains
+            .iter()
+            .any(|domain| domain.eq_ignore_ascii_case(host))
+        {
+            TrustZone::Internal
+        } else {
+            TrustZone::External
+        }
+    }
+
+    /// Whether a call from `origin` may be redirected to `uri`
+    pub fn permits(&self, origin: TrustZone, uri: &str) -> bool {
+        if let Some(pattern) = self.deny_patterns.iter().find(|p| wildcard_match(p, uri)) {
+            debug!("Redirect to {} denied by pattern {}", uri, pattern);
+            return false;
+        }
+        if origin == TrustZone::Trunk
+            && self.zone_of(uri) == TrustZone::Internal
+            && !self.allow_trunk_to_internal
+        {
+            debug!("Redirect from trunk to internal target {} not permitted", uri);
+            return false;
+        }
+        true
+    }
+}
+
+/// Contact of a 3xx response
+#[derive(Debug, Clone, PartialEq)]
+pub struct RedirectContact {
+    pub uri: String,
+    pub q: f32,
+}
+
+/// Contacts of a 3xx response, highest q-value first
+///
+/// Contacts without a q-value count as q=1.0; equal q-values keep their order.
+pub fn redirect_contacts(response: &SipResponse) -> Vec<RedirectContact> {
+    let mut contacts: Vec<RedirectContact> = response
+        .headers()
+        .iter()
+        .filter_map(|h| match h {
+            Header::Contact(contact) => Some(contact.value().to_string()),
+            _ => None,
+        })
+        .flat_map(|value| split_contacts(&value))
+        .filter_map(|entry| parse_contact(&entry))
+        .collect();
+    contacts.sort_by(|a, b| b.q.partial_cmp(&a.q).unwrap_or(std::cmp::Ordering::Equal));
+    contacts
+}
+
+/// Sends an INVITE to a target and returns its final response
+#[async_trait]
+pub trait InviteForwarder: Send + Sync {
+    /// Forward `request` (already retargeted) to `target`
+    ///
+    /// An error means the target could not be reached.
+    async fn forward(&self, target: &str, request: &SipRequest) -> Result<SipResponse, SipError>;
+
+    /// Like [`forward`](Self::forward), sending the status of each
+    /// provisional response on `progress`
+    ///
+    /// Forwarders that do not see provisional responses report none.
+    async fn forward_with_progress(
+        &self,
+        target: &str,
+        request: &SipRequest,
+        progress: mpsc::UnboundedSender<u16>,
+    ) -> Result<SipResponse, SipError> {
+        drop(progress);
+        self.forward(target, request).await
+    }
+}
+
+/// One attempted target
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RedirectHop {
+    pub target: String,
+    /// Final status, `None` when the target was unreachable
+    pub status: Option<u16>,
+    /// Contacts the target redirected to
+    pub redirected_to: Vec<String>,
+}
+
+/// Result of forwarding with redirect recursion
+#[derive(Debug)]
+pub struct RedirectOutcome {
+    /// Response for the caller
+    pub response: SipResponse,
+    /// Target that produced the final response
+    pub target: String,
+    /// Number of 3xx responses followed
+    pub redirect_count: u32,
+    /// Every attempted target, in order
+    pub hops: Vec<RedirectHop>,
+    /// A redirect pointed back to an attempted URI
+    pub loop_detected: bool,
+}
+
+/// Forwards INVITEs and recurses on 3xx responses
+pub struct RedirectFollower {
+    policy: RedirectPolicy,
+    /// Puts our Via on forwarded INVITEs
+    hops: Option<Arc<HopTracker>>,
+    /// Caller's tenant branding, applied to INVITEs leaving the PBX
+    branding: Option<TenantBranding>,
+    /// Header rules of the targets, with the call's template variables
+    header_rules: Option<(Arc<HeaderRulesEngine>, HeaderContext)>,
+}
+
+impl RedirectFollower {
+    pub fn new(policy: RedirectPolicy) -> Self {
+        Self {
+            policy,
+            hops: None,
+            branding: None,
+            header_rules: None,
+        }
+    }
+
+    /// Forward INVITEs with our own Via and branch (see [`HopTracker`])
+    pub fn with_hop_tracker(mut self, hops: Arc<HopTracker>) -> Self {
+        self.hops = Some(hops);
+        self
+    }
+
+    /// Brand INVITEs to targets outside the internal domains (see
+    /// [`brand_request`])
+    pub fn with_branding(mut self, branding: TenantBranding) -> Self {
+        self.branding = Some(branding);
+        self
+    }
+
+    /// Drop the caller's unknown headers and apply each target's outbound
+    /// header rules (see [`HeaderRulesEngine`])
+    pub fn with_header_rules(
+        mut self,
+        header_rules: Arc<HeaderRulesEngine>,
+        context: HeaderContext,
+    ) -> Self {
+        self.header_rules = Some((header_rules, context));
+        self
+    }
+
+    /// Forward `request` to `target`, following redirects per policy
+    ///
+    /// The caller's identity (From, Call-ID) is kept on every retargeted
+    /// INVITE; only the Request-URI and Max-Forwards change (and the Via, with
+    /// a hop tracker). Answers 483 without forwarding when Max-Forwards is 0.
+    pub async fn forward(
+        &self,
+        forwarder: &dyn InviteForwarder,
+        origin: TrustZone,
+        target: &str,
+        request: &SipRequest,
+    ) -> Result<RedirectOutcome, SipError> {
+        if forwarded_max_forwards(request).is_none() {
+            warn!("Not forwarding to {}: Max-Forwards reached 0", target);
+            return Ok(RedirectOutcome {
+                response: ResponseBuilder::new(483).build_for_request(request)?,
+                target: target.to_string(),
+                redirect_count: 0,
+                hops: Vec::new(),
+                loop_detected: false,
+            });
+        }
+
+        let mut pending = VecDeque::from([target.to_string()]);
+        let mut attempted: HashSet<String> = HashSet::new();
+        let mut hops = Vec::new();
+        let mut redirect_count = 0;
+        let mut last_failure: Option<(String, SipResponse)> = None;
+
+        while let Some(target) = pending.pop_front() {
+            attempted.insert(normalize_uri(&target));
+
+            let mut invite = match &self.hops {
+                Some(hops) => hops.forward(request, &target)?,
+                None => retarget(request, &target)?,
+            };
+            if let Some((header_rules, _)) = &self.header_rules {
+                invite = header_rules.strip_unknown(request, &invite, &target)?;
+            }
+            if let Some(branding) = &self.branding {
+                if self.policy.zone_of(&target) != TrustZone::Internal {
+                    invite = brand_request(&invite, branding)?;
+                }
+            }
+            if let Some((header_rules, context)) = &self.header_rules {
+                invite = header_rules.apply_outbound(&invite, &target, context)?;
+            }
+            let response = match forwarder.forward(&target, &invite).await {
+                Ok(response) => response,
+                Err(e) => {
+                    warn!("Target {} unreachable: {}", target, e);
+                    hops.push(RedirectHop {
+                        target,
+                        status: None,
+                        redirected_to: Vec::new(),
+                    });
+                    continue;
+                }
+            };
+            let status = response.status_code();
+
+            if (300..400).contains(&status) {
+                let contacts: Vec<String> = redirect_contacts(&response)
+                    .into_iter()
+                    .map(|c| c.uri)
+                    .collect();
+                hops.push(RedirectHop {
+                    target: target.clone(),
+                    status: Some(status),
+                    redirected_to: contacts.clone(),
+                });
+
+                if contacts.iter().any(|c| attempted.contains(&normalize_uri(c))) {
+                    warn!("Redirect loop detected at {}", target);
+                    return Ok(RedirectOutcome {
+                        response: ResponseBuilder::new(482).build_for_request(request)?,
+                        target,
+                        redirect_count,
+                        hops,
+                        loop_detected: true,
+                    });
+                }
+                if redirect_count >= self.policy.max_redirects {
+                    warn!(
+                        "Not following redirect from {}: limit of {} reached",
+                        target, self.policy.max_redirects
+                    );
+                    last_failure = Some((target, response));
+                    continue;
+                }
+
+                let allowed: Vec<String> = contacts
+                    .into_iter()
+                    .filter(|c| self.policy.permits(origin, c))
+                    .collect();
+                if allowed.is_empty() {
+                    last_failure = Some((target, response));
+                    continue;
+                }
+
+                redirect_count += 1;
+                info!("{} redirected ({}) to {:?}", target, status, allowed);
+                // Redirect targets are tried before the remaining siblings
+                for contact in allowed.into_iter().rev() {
+                    pending.push_front(contact);
+                }
+                continue;
+            }
+
+            hops.push(RedirectHop {
+                target: target.clone(),
+                status: Some(status),
+                redirected_to: Vec::new(),
+            });
+
+            // 2xx ends the search, and so does a global failure
+            if !(300..600).contains(&status) {
+                return Ok(RedirectOutcome {
+                    response,
+                    target,
+                    redirect_count,
+                    hops,
+                    loop_detected: false,
+                });
+            }
+            last_failure = Some((target, response));
+        }
+
+        let (target, response) = match last_failure {
+            Some(failure) => failure,
+            None => (
+                target.to_string(),
+                ResponseBuilder::new(480).build_for_request(request)?,
+            ),
+        };
+        Ok(RedirectOutcome {
+            response,
+            target,
+            redirect_count,
+            hops,
+            loop_detected: false,
+        })
+    }
+}
+
+/// Copy of `request` addressed to `target` with Max-Forwards decremented
+/// (a missing Max-Forwards counts as 70)
+pub fn retarget(request: &SipRequest, target: &str) -> Result<SipRequest, SipError> {
+    let text = String::from_utf8_lossy(&request.to_bytes()).to_string();
+    let (request_line, rest) = text
+        .split_once("\r\n")
+        .ok_or_else(|| SipError::ParseError("Request has no request line".to_string()))?;
+    let method = request_line.split_whitespace().next().unwrap_or("INVITE");
+
+    let (headers, body) = rest.split_once("\r\n\r\n").unwrap_or((rest, ""));
+    let mut has_max_forwards = false;
+    let mut headers: Vec<String> = headers
+        .split("\r\n")
+        .map(|line| match line.split_once(':') {
+            Some((name, value)) if name.trim().eq_ignore_ascii_case("Max-Forwards") => {
+                has_max_forwards = true;
+                let hops: u32 = value.trim().parse().unwrap_or(DEFAULT_MAX_FORWARDS);
+                format!("Max-Forwards: {}", hops.saturating_sub(1))
+            }
+            _ => line.to_string(),
+        })
+        .collect();
+    if !has_max_forwards {
+        headers.push(format!("Max-Forwards: {}", DEFAULT_MAX_FORWARDS - 1));
+    }
+
+    let retargeted = format!(
+        "{} {} SIP/2.0\r\n{}\r\n\r\n{}",
+        method,
+        target,
+        headers.join("\r\n"),
+        body
+    );
+    SipRequest::parse(retargeted.as_bytes())
+}
+
+/// Split a Contact header value into entries (commas inside `<>` are kept)
+fn split_contacts(value: &str) -> Vec<String> {
+    let mut entries = Vec::new();
+    let mut current = String::new();
+    let mut in_brackets = false;
+    let mut in_quotes = false;
+    for c in value.chars() {
+        match c {
+            '"' => in_quotes = !in_quotes,
+            '<' if !in_quotes => in_brackets = true,
+            '>' if !in_quotes => in_brackets = false,
+            ',' if !in_brackets && !in_quotes => {
+                entries.push(std::mem::take(&mut current));
+                continue;
+            }
+            _ => {}
+        }
+        current.push(c);
+    }
+    entries.push(current);
+    entries
+        .into_iter()
+        .map(|e| e.trim().to_string())
+        .filter(|e| !e.is_empty())
+        .collect()
+}
+
+fn parse_contact(entry: &str) -> Option<RedirectContact> {
+    let (uri, params) = match (entry.find('<'), entry.find('>')) {
+        (Some(start), Some(end)) if start < end => (&entry[start + 1..end], &entry[end + 1..]),
+        _ => match entry.split_once(';') {
+            Some((uri, params)) => (uri, params),
+            None => (entry, ""),
+        },
+    };
+    let uri = uri.trim();
+    if uri.is_empty() || uri == "*" {
+        return None;
+    }
+
+    let q = params
+        .split(';')
+        .find_map(|p| p.trim().strip_prefix("q="))
+        .and_then(|q| q.trim().parse().ok())
+        .unwrap_or(1.0);
+    Some(RedirectContact {
+        uri: uri.to_string(),
+        q,
+    })
+}
+
+/// Host part of a SIP URI (without port or parameters)
+pub(crate) fn uri_host(uri: &str) -> &str {
+    let rest = uri
+        .strip_prefix("sips:")
+        .or_else(|| uri.strip_prefix("sip:"))
+        .unwrap_or(uri);
+    let host_port = rest.rsplit_once('@').map(|(_, h)| h).unwrap_or(rest);
+    let host_port = host_port.split([';', '?']).next().unwrap_or_default();
+    if host_port.starts_with('[') {
+        host_port.split(']').next().map(|h| &h[1..]).unwrap_or(host_port)
+    } else {
+        host_port.split(':').next().unwrap_or(host_port)
+    }
+}
+
+/// URI used for loop detection: scheme, user and host, lowercased, no parameters
+fn normalize_uri(uri: &str) -> String {
+    uri.split([';', '?'])
+        .next()
+        .unwrap_or(uri)
+        .trim()
+        .to_lowercase()
+}
+
+/// Glob match where `*` matches any run of characters
+pub(crate) fn wildcard_match(pattern: &str, text: &str) -> bool {
+    let parts: Vec<&str> = pattern.split('*').collect();
+    if parts.len() == 1 {
+        return pattern == text;
+    }
+
+    let mut rest = text;
+    for (i, part) in parts.iter().enumerate() {
+        if i == 0 {
+            match rest.strip_prefix(part) {
+                Some(r) => rest = r,
+                None => return false,
+            }
+        } else if i == parts.len() - 1 {
+            return rest.ends_with(part);
+        } else {
+            match rest.find(part) {
+                Some(pos) => rest = &rest[pos + part.len()..],
+                None => return false,
+            }
+        }
+    }
+    true
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+    use std::sync::Mutex;
+
+    /// UAS answering each target with a canned response
+    #[derive(Default)]
+    struct ScriptedUas {
+        responses: HashMap<String, String>,
+        attempts: Mutex<Vec<String>>,
+    }
+
+    impl ScriptedUas {
+        fn respond(mut self, target: &str, status_line: &str, extra: &str) -> Self {
+            self.responses.insert(
+                target.to_string(),
+                format!(
+                    "SIP/2.0 {}\r\nVia: SIP/2.0/UDP 192.0.2.1:5060;branch=z9hG4bK1\r\n\
+                     From: <sip:alice@pbx.example.com>;tag=a1\r\nTo: <sip:svc@trunk.example.net>;tag=b1\r\n\
+                     Call-ID: redirect-test\r\nCSeq: 1 INVITE\r\n{}Content-Length: 0\r\n\r\n",
+                    status_line, extra
+                ),
+            );
+            self
+        }
+    }
+
+    #[async_trait]
+    impl InviteForwarder for ScriptedUas {
+        async fn forward(&self, target: &str, request: &SipRequest) -> Result<SipResponse, SipError> {
+            assert_eq!(request.uri().to_string(), target);
+            self.attempts.lock().unwrap().push(target.to_string());
+            match self.responses.get(target) {
+                Some(response) => SipResponse::parse(response.as_bytes()),
+                None => Err(SipError::TransportError(format!("{} unreachable", target))),
+            }
+        }
+    }
+
+    fn invite() -> SipRequest {
+        let text = "INVITE sip:svc@trunk.example.net SIP/2.0\r\n\
+            Via: SIP/2.0/UDP 192.0.2.1:5060;branch=z9hG4bK1\r\n\
+            Max-Forwards: 70\r\n\
+            From: \"Alice\" <sip:alice@pbx.example.com>;tag=a1\r\n\
+            To: <sip:svc@trunk.example.net>\r\n\
+            Call-ID: redirect-test\r\n\
+            CSeq: 1 INVITE\r\n\
+            Content-Length: 0\r\n\r\n";
+        SipRequest::parse(text.as_bytes()).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_302_falls_through_to_second_contact() {
+        let uas = ScriptedUas::default()
+            .respond(
+                "sip:svc@trunk.example.net",
+                "302 Moved Temporarily",
+                "Contact: <sip:svc@10.0.0.2>;q=0.5, <sip:svc@10.0.0.1>;q=0.9\r\n",
+            )
+            .respond("sip:svc@10.0.0.2", "200 OK", "");
+
+        let follower = RedirectFollower::new(RedirectPolicy::default());
+        let outcome = follower
+            .forward(&uas, TrustZone::Internal, "sip:svc@trunk.example.net", &invite())
+            .await
+            .unwrap();
+
+        assert_eq!(outcome.response.status_code(), 200);
+        assert_eq!(outcome.target, "sip:svc@10.0.0.2");
+        assert_eq!(outcome.redirect_count, 1);
+        // Higher q first; 10.0.0.1 is unreachable
+        assert_eq!(
+            *uas.attempts.lock().unwrap(),
+            vec!["sip:svc@trunk.example.net", "sip:svc@10.0.0.1", "sip:svc@10.0.0.2"]
+        );
+        assert_eq!(outcome.hops.len(), 3);
+        assert_eq!(outcome.hops[1].status, None);
+    }
+
+    #[tokio::test]
+    async fn test_redirect_loop_returns_482() {
+        let uas = ScriptedUas::default()
+            .respond("sip:svc@trunk.example.net", "302 Moved Temporarily", "Contact: <sip:a@10.0.0.1>\r\n")
+            .respond("sip:a@10.0.0.1", "302 Moved Temporarily", "Contact: <sip:svc@trunk.example.net>\r\n");
+
+        let follower = RedirectFollower::new(RedirectPolicy::default());
+        let outcome = follower
+            .forward(&uas, TrustZone::Internal, "sip:svc@trunk.example.net", &invite())
+            .await
+            .unwrap();
+
+        assert!(outcome.loop_detected);
+        assert_eq!(outcome.response.status_code(), 482);
+    }
+
+    #[tokio::test]
+    async fn test_policy_limits() {
+        let policy = RedirectPolicy {
+            deny_patterns: vec!["sip:*@premium.example.net".to_string()],
+            internal_domains: vec!["pbx.example.com".to_string()],
+            ..Default::default()
+        };
+        assert!(!policy.permits(TrustZone::Internal, "sip:1900@premium.example.net"));
+        assert!(!policy.permits(TrustZone::Trunk, "sip:1001@pbx.example.com"));
+        assert!(policy.permits(TrustZone::Internal, "sip:1001@pbx.example.com"));
+
+        // Only denied contacts: the 302 goes back as the final response
+        let uas = ScriptedUas::default().respond(
+            "sip:svc@trunk.example.net",
+            "302 Moved Temporarily",
+            "Contact: <sip:1900@premium.example.net>, <sip:1001@pbx.example.com>\r\n",
+        );
+        let outcome = RedirectFollower::new(policy)
+            .forward(&uas, TrustZone::Trunk, "sip:svc@trunk.example.net", &invite())
+            .await
+            .unwrap();
+        assert_eq!(outcome.response.status_code(), 302);
+        assert_eq!(outcome.redirect_count, 0);
+        assert_eq!(uas.attempts.lock().unwrap().len(), 1);
+    }
+}
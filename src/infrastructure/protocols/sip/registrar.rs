@@ -1,19 +1,32 @@
 //! SIP Registrar - manages endpoint registrations
 
+use super::address::uri_from_header;
 use super::auth::SipAuthenticator;
 use super::builder::{build_register_response, ResponseBuilder};
 use super::handler::SipHandler;
+use super::maintenance::MaintenanceRegistry;
 use super::message::{SipError, SipMethod, SipRequest, SipResponse};
+use super::registration_events::{
+    ChurnConfig, RegistrationEvent, RegistrationEventLog, RegistrationEventType,
+};
+use super::rport::extract_received_from_via;
+use super::survivability::{SurvivabilityManager, SurvivableRegistration};
+use crate::domain::routing::simulation::{Explain, Explanation, RouteOutcome, RoutedCall};
+use crate::domain::shared::value_objects::SipUri;
+use crate::infrastructure::clock::{system_clock, Clock};
 use async_trait::async_trait;
 use chrono::{DateTime, Duration, Utc};
+use rsip::headers::UntypedHeader;
 use rsip::Header;
+use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::Arc;
-use tokio::sync::RwLock;
+use tokio::sync::{broadcast, RwLock};
 use tracing::{debug, info, warn};
 
 /// Registration binding
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Binding {
     /// Contact URI
     pub contact: String,
@@ -25,14 +38,18 @@ pub struct Binding {
 
 impl Binding {
     pub fn is_expired(&self) -> bool {
-        Utc::now() > self.expires_at
+        self.is_expired_at(Utc::now())
+    }
+
+    pub fn is_expired_at(&self, now: DateTime<Utc>) -> bool {
+        now > self.expires_at
     }
 }
 
 /// Registration entry for an Address of Record (AoR)
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Registration {
-    /// Address of Record (e.g., sip:alice@example.com)
+    /// Address of Record in canonical form (e.g., sip:alice@example.com)
     pub aor: String,
     /// Contact bindings
     pub bindings: Vec<Binding>,
@@ -40,8 +57,8 @@ pub struct Registration {
 
 /// In-memory registrar
 pub struct Registrar {
-    /// Map of AoR to Registration
-    registrations: Arc<RwLock<HashMap<String, Registration>>>,
+    /// Map of AoR (see [`aor_key`]) to Registration
+    registrations: Arc<RwLock<HashMap<SipUri, Registration>>>,
     /// Default expiration time (seconds)
     default_expires: u32,
     /// Maximum expiration time (seconds)
@@ -50,6 +67,16 @@ pub struct Registrar {
     min_expires: u32,
     /// Optional digest authentication
     auth: Option<Arc<dyn SipAuthenticator>>,
+    /// Registration events, history and churn detection
+    events: Arc<RegistrationEventLog>,
+    /// Hot standby: bindings come from the active node only
+    passive: AtomicBool,
+    /// Time source of binding expiry
+    clock: Arc<dyn Clock>,
+    /// Tenants in maintenance, whose registrations are flagged
+    maintenance: Option<Arc<MaintenanceRegistry>>,
+    /// Branch survivability, which decides on phones of the central system
+    survivability: Option<Arc<SurvivabilityManager>>,
 }
 
 impl Registrar {
@@ -60,6 +87,11 @@ impl Registrar {
             max_expires: 7200,     // 2 hours
             min_expires: 60,       // 1 minute
             auth: None,
+            events: Arc::new(RegistrationEventLog::default()),
+            passive: AtomicBool::new(false),
+            clock: system_clock(),
+            maintenance: None,
+            survivability: None,
         }
     }
 
@@ -71,9 +103,66 @@ impl Registrar {
             max_expires: 7200,
             min_expires: 60,
             auth: Some(auth),
+            events: Arc::new(RegistrationEventLog::default()),
+            passive: AtomicBool::new(false),
+            clock: system_clock(),
+            maintenance: None,
+            survivability: None,
         }
     }
 
+    /// Time source of binding expiry (the system clock by default)
+    pub fn with_clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    /// Flag registrations from tenants in maintenance
+    pub fn with_maintenance(mut self, maintenance: Arc<MaintenanceRegistry>) -> Self {
+        self.maintenance = Some(maintenance);
+        self
+    }
+
+    /// Refuse phones of the central system unless its upstream is lost,
+    /// then accept them with a short expiry
+    pub fn with_survivability(mut self, survivability: Arc<SurvivabilityManager>) -> Self {
+        self.survivability = Some(survivability);
+        self
+    }
+
+    /// Set churn detection thresholds (clears recorded history)
+    pub fn set_churn_config(&mut self, config: ChurnConfig) {
+        self.events = Arc::new(RegistrationEventLog::new(config));
+    }
+
+    /// Subscribe to registration events and churn warnings
+    pub fn subscribe_events(&self) -> broadcast::Receiver<RegistrationEvent> {
+        self.events.subscribe()
+    }
+
+    /// Recent registration events for an AoR, oldest first
+    pub fn registration_history(&self, aor: &str) -> Vec<RegistrationEvent> {
+        self.events.history(aor)
+    }
+
+    /// Recent registration events for all AoRs of a user, oldest first
+    pub fn registration_history_for_user(&self, username: &str) -> Vec<RegistrationEvent> {
+        self.events.history_for_user(username)
+    }
+
+    /// Switch standby (passive) mode
+    ///
+    /// A passive registrar rejects REGISTER and neither purges expired
+    /// bindings nor records events; its bindings are replaced by the
+    /// active node through `replace_registration` and `restore`.
+    pub fn set_passive(&self, passive: bool) {
+        self.passive.store(passive, Ordering::SeqCst);
+    }
+
+    pub fn is_passive(&self) -> bool {
+        self.passive.load(Ordering::SeqCst)
+    }
+
     /// Set authentication (for existing registrar)
     pub fn set_auth(&mut self, auth: Arc<dyn SipAuthenticator>) {
         self.auth = Some(auth);
@@ -97,7 +186,7 @@ impl Registrar {
         contact: String,
         expires: u32,
     ) -> Result<(), SipError> {
-        self.register_binding(&aor, &contact, expires, None).await
+        self.register_binding(&aor, &contact, expires, None, None).await
     }
 
     /// Register a binding
@@ -107,91 +196,260 @@ impl Registrar {
         contact: &str,
         expires: u32,
         user_agent: Option<String>,
+        source_ip: Option<String>,
     ) -> Result<(), SipError> {
+        let key = aor_key(aor)
+            .ok_or_else(|| SipError::InvalidMessage(format!("Invalid address of record '{}'", aor)))?;
         let mut registrations = self.registrations.write().await;
 
         if expires == 0 {
             // Unregister
             info!("Unregistering: {}", aor);
-            registrations.remove(aor);
+            let removed = registrations.remove(&key).map(|r| r.bindings).unwrap_or_default();
+            drop(registrations);
+
+            for binding in removed {
+                self.events.record(
+                    RegistrationEvent::binding(
+                        RegistrationEventType::Removed,
+                        aor,
+                        &binding.contact,
+                        0,
+                    )
+                    .with_user_agent(binding.user_agent.or_else(|| user_agent.clone()))
+                    .with_source_ip(source_ip.clone()),
+                );
+            }
             return Ok(());
         }
 
-        let expires_at = Utc::now() + Duration::seconds(expires as i64);
+        let expires_at = self.clock.now() + Duration::seconds(expires as i64);
         let binding = Binding {
             contact: contact.to_string(),
             expires_at,
-            user_agent,
+            user_agent: user_agent.clone(),
         };
 
         let registration = registrations
-            .entry(aor.to_string())
+            .entry(key.clone())
             .or_insert_with(|| Registration {
-                aor: aor.to_string(),
+                aor: key.to_string(),
                 bindings: Vec::new(),
             });
 
         // Remove existing binding with same contact
+        let existing = registration.bindings.len();
         registration
             .bindings
             .retain(|b| b.contact != contact);
+        let replaced = registration.bindings.len() < existing;
 
         // Add new binding
         registration.bindings.push(binding);
+        drop(registrations);
 
         info!(
             "Registered: {} -> {} (expires in {}s)",
             aor, contact, expires
         );
 
+        let event_type = if replaced {
+            RegistrationEventType::Refreshed
+        } else {
+            RegistrationEventType::Added
+        };
+        self.events.record(
+            RegistrationEvent::binding(event_type, aor, contact, expires)
+                .with_user_agent(user_agent)
+                .with_source_ip(source_ip),
+        );
+
         Ok(())
     }
 
     /// Get bindings for an AoR
     pub async fn get_bindings(&self, aor: &str) -> Option<Vec<Binding>> {
+        self.bindings_of(&aor_key(aor)?).await
+    }
+
+    /// Get bindings for an AoR already made a key
+    async fn bindings_of(&self, key: &SipUri) -> Option<Vec<Binding>> {
+        if self.is_passive() {
+            let now = self.clock.now();
+            let registrations = self.registrations.read().await;
+            let bindings: Vec<Binding> = registrations
+                .get(key)?
+                .bindings
+                .iter()
+                .filter(|b| !b.is_expired_at(now))
+                .cloned()
+                .collect();
+            return if bindings.is_empty() { None } else { Some(bindings) };
+        }
+
         let mut registrations = self.registrations.write().await;
 
-        if let Some(registration) = registrations.get_mut(aor) {
+        if let Some(registration) = registrations.get_mut(key) {
             // Remove expired bindings
-            registration.bindings.retain(|b| !b.is_expired());
-
-            if registration.bindings.is_empty() {
-                registrations.remove(aor);
-                return None;
+            let expired = self.take_expired(registration);
+            let bindings = registration.bindings.clone();
+            let aor = registration.aor.clone();
+            if bindings.is_empty() {
+                registrations.remove(key);
             }
+            drop(registrations);
 
-            return Some(registration.bindings.clone());
+            self.record_expired(&aor, expired);
+            return if bindings.is_empty() { None } else { Some(bindings) };
         }
 
         None
     }
 
+    /// Split expired bindings off a registration
+    fn take_expired(&self, registration: &mut Registration) -> Vec<Binding> {
+        let now = self.clock.now();
+        let (expired, valid): (Vec<Binding>, Vec<Binding>) = registration
+            .bindings
+            .drain(..)
+            .partition(|b| b.is_expired_at(now));
+        registration.bindings = valid;
+        expired
+    }
+
+    fn record_expired(&self, aor: &str, expired: Vec<Binding>) {
+        for binding in expired {
+            self.events.record(
+                RegistrationEvent::binding(RegistrationEventType::Expired, aor, &binding.contact, 0)
+                    .with_user_agent(binding.user_agent),
+            );
+        }
+    }
+
     /// Get all registered users (AoRs)
     pub async fn get_all_registrations(&self) -> Vec<Registration> {
+        if self.is_passive() {
+            return self.snapshot().await;
+        }
+
         let mut registrations = self.registrations.write().await;
 
         // Remove expired bindings and collect valid registrations
         let mut valid_registrations = Vec::new();
         let mut expired_aors = Vec::new();
+        let mut expired_bindings = Vec::new();
 
-        for (aor, registration) in registrations.iter_mut() {
-            registration.bindings.retain(|b| !b.is_expired());
+        for (key, registration) in registrations.iter_mut() {
+            let expired = self.take_expired(registration);
+            if !expired.is_empty() {
+                expired_bindings.push((registration.aor.clone(), expired));
+            }
 
             if registration.bindings.is_empty() {
-                expired_aors.push(aor.clone());
+                expired_aors.push(key.clone());
             } else {
                 valid_registrations.push(registration.clone());
             }
         }
 
         // Remove expired registrations
-        for aor in expired_aors {
-            registrations.remove(&aor);
+        for key in expired_aors {
+            registrations.remove(&key);
+        }
+        drop(registrations);
+
+        for (aor, expired) in expired_bindings {
+            self.record_expired(&aor, expired);
         }
 
         valid_registrations
     }
 
+    /// Unexpired registrations, without purging anything
+    pub async fn snapshot(&self) -> Vec<Registration> {
+        let now = self.clock.now();
+        let registrations = self.registrations.read().await;
+        registrations
+            .values()
+            .filter_map(|registration| {
+                let bindings: Vec<Binding> = registration
+                    .bindings
+                    .iter()
+                    .filter(|b| !b.is_expired_at(now))
+                    .cloned()
+                    .collect();
+                (!bindings.is_empty()).then(|| Registration {
+                    aor: registration.aor.clone(),
+                    bindings,
+                })
+            })
+            .collect()
+    }
+
+    /// Current bindings of an AoR, expired ones included
+    pub async fn registration(&self, aor: &str) -> Option<Registration> {
+        let key = aor_key(aor)?;
+        self.registrations.read().await.get(&key).cloned()
+    }
+
+    /// Replace the bindings of an AoR (no bindings removes it)
+    pub async fn replace_registration(&self, aor: &str, bindings: Vec<Binding>) {
+        let Some(key) = aor_key(aor) else {
+            warn!("Ignoring bindings of invalid address of record '{}'", aor);
+            return;
+        };
+        let mut registrations = self.registrations.write().await;
+        if bindings.is_empty() {
+            registrations.remove(&key);
+        } else {
+            registrations.insert(
+                key.clone(),
+                Registration {
+                    aor: key.to_string(),
+                    bindings,
+                },
+            );
+        }
+    }
+
+    /// Replace all registrations
+    pub async fn restore(&self, snapshot: Vec<Registration>) {
+        let mut registrations = self.registrations.write().await;
+        registrations.clear();
+        for mut registration in snapshot {
+            match aor_key(&registration.aor) {
+                Some(key) => {
+                    registration.aor = key.to_string();
+                    registrations.insert(key, registration);
+                }
+                None => warn!("Skipping registration of invalid address of record '{}'", registration.aor),
+            }
+        }
+    }
+
+    /// Remove expired bindings of every AoR; returns how many were removed
+    pub async fn purge_expired(&self) -> usize {
+        if self.is_passive() {
+            return 0;
+        }
+        let mut registrations = self.registrations.write().await;
+        let mut expired_bindings = Vec::new();
+        for registration in registrations.values_mut() {
+            let expired = self.take_expired(registration);
+            if !expired.is_empty() {
+                expired_bindings.push((registration.aor.clone(), expired));
+            }
+        }
+        registrations.retain(|_, registration| !registration.bindings.is_empty());
+        drop(registrations);
+
+        let removed = expired_bindings.iter().map(|(_, expired)| expired.len()).sum();
+        for (aor, expired) in expired_bindings {
+            self.record_expired(&aor, expired);
+        }
+        removed
+    }
+
     /// Get registration count
     pub async fn get_registration_count(&self) -> usize {
         let registrations = self.registrations.read().await;
@@ -203,6 +461,12 @@ impl Registrar {
         self.get_bindings(aor).await.is_some()
     }
 
+    /// Bindings of the AoR a request URI addresses, e.g. of
+    /// `sip:alice@example.com` for `sip:Alice@example.com;transport=udp`
+    pub async fn bindings_for_uri(&self, uri: &SipUri) -> Option<Vec<Binding>> {
+        self.bindings_of(&uri_key(uri)).await
+    }
+
     /// Extract AoR from request
     fn extract_aor(request: &SipRequest) -> Result<String, SipError> {
         // Get To header
@@ -221,9 +485,16 @@ impl Registrar {
     }
 
     /// Extract Contact from request
+    ///
+    /// rsip cannot parse bracketed IPv6 hosts, so such contacts are taken
+    /// from the raw header value.
     fn extract_contact(request: &SipRequest) -> Option<String> {
         request.headers().iter().find_map(|h| match h {
-            Header::Contact(contact) => contact.uri().ok().map(|u| u.to_string()),
+            Header::Contact(contact) => contact
+                .uri()
+                .ok()
+                .map(|u| u.to_string())
+                .or_else(|| Some(uri_from_header(contact.value()).to_string())),
             _ => None,
         })
     }
@@ -232,7 +503,7 @@ impl Registrar {
     fn extract_expires(request: &SipRequest) -> Option<u32> {
         // Try Expires header first
         if let Some(expires) = request.headers().iter().find_map(|h| match h {
-            Header::Expires(exp) => exp.to_string().parse().ok(),
+            Header::Expires(exp) => exp.value().trim().parse().ok(),
             _ => None,
         }) {
             return Some(expires);
@@ -242,6 +513,27 @@ impl Registrar {
         None
     }
 
+    /// Source address of the request, from the top Via (`received` or sent-by host)
+    fn extract_source_ip(request: &SipRequest) -> Option<String> {
+        let via = request.headers().iter().find_map(|h| match h {
+            Header::Via(via) => Some(via.to_string()),
+            _ => None,
+        })?;
+        let via = via.strip_prefix("Via:").unwrap_or(&via).trim();
+
+        if let Some(received) = extract_received_from_via(via) {
+            return Some(received);
+        }
+
+        // "SIP/2.0/UDP host:port;params"
+        let sent_by = via.split(';').next()?.split_whitespace().nth(1)?;
+        let host = match sent_by.strip_prefix('[') {
+            Some(v6) => v6.split(']').next()?,
+            None => sent_by.split(':').next()?,
+        };
+        Some(host.to_string())
+    }
+
     /// Extract User-Agent from request
     fn extract_user_agent(request: &SipRequest) -> Option<String> {
         request.headers().iter().find_map(|h| match h {
@@ -251,6 +543,20 @@ impl Registrar {
     }
 }
 
+/// Registration key of an AoR, a bare URI or a name-addr (`"Alice" <sip:..>`)
+fn aor_key(aor: &str) -> Option<SipUri> {
+    let uri = if aor.contains('<') { uri_from_header(aor) } else { aor };
+    SipUri::parse(uri).ok().map(|uri| uri_key(&uri))
+}
+
+/// Registration key of a URI: its address of record, the user part in lower
+/// case as phones and dial plans disagree on the case of account names
+fn uri_key(uri: &SipUri) -> SipUri {
+    let aor = uri.address_of_record();
+    let user = aor.user().to_lowercase();
+    aor.with_user(&user)
+}
+
 impl Default for Registrar {
     fn default() -> Self {
         Self::new()
@@ -262,6 +568,14 @@ impl SipHandler for Registrar {
     async fn handle_request(&self, request: SipRequest) -> Result<SipResponse, SipError> {
         debug!("Handling REGISTER request");
 
+        // A standby node only serves registrations replicated to it
+        if self.is_passive() {
+            warn!("REGISTER received while on standby - rejecting");
+            return ResponseBuilder::new(503)
+                .header(Header::Other("Retry-After".to_string(), "5".to_string()))
+                .build_for_request(&request);
+        }
+
         // Check authentication if enabled
         if let Some(auth) = &self.auth {
             // Check if Authorization header is present
@@ -307,16 +621,62 @@ impl SipHandler for Registrar {
         let contact = Self::extract_contact(&request);
         let requested_expires = Self::extract_expires(&request);
         let user_agent = Self::extract_user_agent(&request);
+        let source_ip = Self::extract_source_ip(&request);
 
         // Get effective expiration time
-        let expires = self.get_expires(requested_expires);
+        let mut expires = self.get_expires(requested_expires);
+
+        // Phones of the central system register here only while it is
+        // unreachable, and briefly, so they go back once it returns
+        let survivability = self
+            .survivability
+            .as_ref()
+            .map_or(SurvivableRegistration::Normal, |s| s.registration(&aor));
+        match survivability {
+            SurvivableRegistration::Normal => {}
+            SurvivableRegistration::Refused => {
+                debug!("Registration of {} refused: belongs upstream", aor);
+                return ResponseBuilder::new(503)
+                    .header(Header::Other(
+                        "Warning".to_string(),
+                        "399 yakyak \"Register with the central system\"".to_string(),
+                    ))
+                    .build_for_request(&request);
+            }
+            SurvivableRegistration::Accepted { expires: limit } => {
+                expires = expires.min(limit);
+                info!("Registration of {} accepted in survivability ({}s)", aor, expires);
+            }
+        }
 
         // Register the binding if contact is present
         if let Some(contact_uri) = contact.as_ref() {
-            self.register_binding(&aor, contact_uri, expires, user_agent)
+            self.register_binding(&aor, contact_uri, expires, user_agent, source_ip)
                 .await?;
         }
 
+        // Accepted, but the UA is told its tenant is in maintenance
+        let in_maintenance = self
+            .maintenance
+            .as_ref()
+            .is_some_and(|maintenance| maintenance.flag_registration(&aor));
+        if in_maintenance {
+            debug!("Registration of {} flagged: tenant in maintenance", aor);
+            return ResponseBuilder::new(200)
+                .header(Header::Other(
+                    "Warning".to_string(),
+                    "399 yakyak \"Tenant in maintenance\"".to_string(),
+                ))
+                .build_for_request(&request);
+        }
+
+        // Phones are told the shortened expiry, or they would refresh too late
+        if let SurvivableRegistration::Accepted { .. } = survivability {
+            return ResponseBuilder::new(200)
+                .header(Header::Other("Expires".to_string(), expires.to_string()))
+                .build_for_request(&request);
+        }
+
         // Build response
         let response = build_register_response(&request, 200)?;
 
@@ -328,6 +688,22 @@ impl SipHandler for Registrar {
     }
 }
 
+#[async_trait]
+impl Explain for Registrar {
+    fn step(&self) -> &'static str {
+        "registrar"
+    }
+
+    async fn explain(&self, call: &RoutedCall) -> Option<Explanation> {
+        let bindings = self.get_bindings(&call.target_uri).await?;
+        let contacts: Vec<String> = bindings.into_iter().map(|b| b.contact).collect();
+        Some(
+            Explanation::matched(format!("{} registered devices", contacts.len()))
+                .ending(RouteOutcome::Ring { contacts }),
+        )
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -343,6 +719,7 @@ mod tests {
                 "sip:alice@192.168.1.100:5060",
                 3600,
                 Some("YakYak/0.1".to_string()),
+                None,
             )
             .await
             .unwrap();
@@ -367,13 +744,14 @@ mod tests {
                 "sip:bob@192.168.1.101:5060",
                 3600,
                 None,
+                None,
             )
             .await
             .unwrap();
 
         // Unregister (expires = 0)
         registrar
-            .register_binding("sip:bob@example.com", "sip:bob@192.168.1.101:5060", 0, None)
+            .register_binding("sip:bob@example.com", "sip:bob@192.168.1.101:5060", 0, None, None)
             .await
             .unwrap();
 
@@ -381,4 +759,84 @@ mod tests {
         let bindings = registrar.get_bindings("sip:bob@example.com").await;
         assert!(bindings.is_none());
     }
+
+    #[tokio::test]
+    async fn test_lookup_ignores_case_and_uri_parameters() {
+        let registrar = Registrar::new();
+        registrar
+            .register_binding("sip:Alice@Example.COM", "sip:alice@192.168.1.100:5060", 3600, None, None)
+            .await
+            .unwrap();
+
+        let invited = SipUri::parse("sip:alice@example.com;transport=udp").unwrap();
+        let bindings = registrar.bindings_for_uri(&invited).await.unwrap();
+        assert_eq!(bindings[0].contact, "sip:alice@192.168.1.100:5060");
+        assert!(registrar.is_registered("\"Alice\" <sip:alice@example.com>").await);
+        assert_eq!(registrar.get_all_registrations().await[0].aor, "sip:alice@example.com");
+
+        assert!(registrar
+            .add_binding("alice".to_string(), "sip:alice@192.168.1.100:5060".to_string(), 3600)
+            .await
+            .is_err());
+        assert_eq!(registrar.get_registration_count().await, 1);
+    }
+
+    #[tokio::test]
+    async fn test_flapping_device_raises_churn() {
+        let mut registrar = Registrar::new();
+        registrar.set_churn_config(ChurnConfig {
+            max_registrations: 5,
+            ..Default::default()
+        });
+        let mut events = registrar.subscribe_events();
+        let aor = "sip:carol@example.com";
+
+        // Two phones share carol's credentials and keep replacing each other
+        for i in 0..4 {
+            for (ua, ip) in [("DeskPhone/1.0", "10.0.0.5"), ("SoftPhone/2.3", "203.0.113.9")] {
+                let contact = format!("sip:carol@{}:5060", ip);
+                registrar
+                    .register_binding(aor, &contact, 3600, Some(ua.to_string()), Some(ip.to_string()))
+                    .await
+                    .unwrap();
+                if i % 2 == 1 {
+                    registrar
+                        .register_binding(aor, &contact, 0, Some(ua.to_string()), Some(ip.to_string()))
+                        .await
+                        .unwrap();
+                }
+            }
+        }
+
+        let mut churn = Vec::new();
+        while let Ok(event) = events.try_recv() {
+            if event.event_type == RegistrationEventType::Churn {
+                churn.push(event);
+            }
+        }
+        assert_eq!(churn.len(), 1);
+        match churn[0].churn.as_ref().unwrap() {
+            super::super::registration_events::ChurnCause::CompetingDevices {
+                user_agents,
+                source_ips,
+            } => {
+                assert_eq!(user_agents.len(), 2);
+                assert!(source_ips.contains(&"203.0.113.9".to_string()));
+            }
+            other => panic!("unexpected cause: {:?}", other),
+        }
+
+        let history = registrar.registration_history_for_user("carol");
+        assert_eq!(history, registrar.registration_history(aor));
+        assert_eq!(history.len(), 15);
+        assert_eq!(history[0].event_type, RegistrationEventType::Added);
+        assert_eq!(history[0].user_agent.as_deref(), Some("DeskPhone/1.0"));
+        assert!(history.iter().any(|e| e.event_type == RegistrationEventType::Churn));
+        assert!(history
+            .iter()
+            .any(|e| e.event_type == RegistrationEventType::Removed && e.source_ip.as_deref() == Some("10.0.0.5")));
+        assert!(history
+            .iter()
+            .any(|e| e.event_type == RegistrationEventType::Refreshed && e.replaced_existing));
+    }
 }
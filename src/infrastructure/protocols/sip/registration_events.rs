@@ -0,0 +1,401 @@
+//! Registrar observability: per-binding events, per-AoR history and churn detection
+//!
+//! Every add/refresh/expire/remove performed by the [`Registrar`] is turned
+//! into a [`RegistrationEvent`], counted in `registration_events_total{type}`,
+//! published to subscribers and kept in a small per-AoR ring buffer. Devices
+//! that register too often within a window raise a churn warning naming the
+//! likely cause when it can be told from the recent events.
+//!
+//! [`Registrar`]: super::registrar::Registrar
+
+use chrono::{DateTime, Duration, Utc};
+use metrics::counter;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::sync::Mutex;
+use tokio::sync::broadcast;
+use tracing::warn;
+
+/// Number of events kept per AoR
+pub const REGISTRATION_HISTORY_SIZE: usize = 20;
+
+/// Kind of registration event
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum RegistrationEventType {
+    /// New binding for a contact
+    Added,
+    /// Existing binding re-registered
+    Refreshed,
+    /// Binding lapsed without a refresh
+    Expired,
+    /// Binding removed by the device (Expires: 0)
+    Removed,
+    /// Too many registrations for one AoR within the churn window
+    Churn,
+}
+
+impl RegistrationEventType {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            RegistrationEventType::Added => "added",
+            RegistrationEventType::Refreshed => "refreshed",
+            RegistrationEventType::Expired => "expired",
+            RegistrationEventType::Removed => "removed",
+            RegistrationEventType::Churn => "churn",
+        }
+    }
+}
+
+/// Likely cause of registration churn
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "cause", rename_all = "snake_case")]
+pub enum ChurnCause {
+    /// Registration interval is shorter than the NAT binding timeout
+    ShortExpires { expires: u32, nat_timeout: u32 },
+    /// Several devices share the same credentials and keep replacing each other
+    CompetingDevices {
+        user_agents: Vec<String>,
+        source_ips: Vec<String>,
+    },
+    /// Nothing in the recent events points at a cause
+    Unknown,
+}
+
+impl ChurnCause {
+    /// Human readable description for logs and dashboards
+    pub fn describe(&self) -> String {
+        match self {
+            ChurnCause::ShortExpires { expires, nat_timeout } => format!(
+                "registration interval {}s is shorter than the NAT timeout {}s",
+                expires, nat_timeout
+            ),
+            ChurnCause::CompetingDevices {
+                user_agents,
+                source_ips,
+            } => format!(
+                "multiple devices using the same credentials (user agents: {}; source IPs: {})",
+                user_agents.join(", "),
+                source_ips.join(", ")
+            ),
+            ChurnCause::Unknown => "cause unknown".to_string(),
+        }
+    }
+}
+
+/// A single registrar event
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RegistrationEvent {
+    #[serde(rename = "type")]
+    pub event_type: RegistrationEventType,
+    pub aor: String,
+    pub contact: Option<String>,
+    pub source_ip: Option<String>,
+    pub user_agent: Option<String>,
+    /// Granted expiration (seconds); 0 for removals and expirations
+    pub expires: u32,
+    /// Whether the event replaced an existing binding for the same contact
+    pub replaced_existing: bool,
+    pub timestamp: DateTime<Utc>,
+    /// Set on churn warnings
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub churn: Option<ChurnCause>,
+}
+
+impl RegistrationEvent {
+    /// Create a binding event
+    pub fn binding(
+        event_type: RegistrationEventType,
+        aor: &str,
+        contact: &str,
+        expires: u32,
+    ) -> Self {
+        Self {
+            event_type,
+            aor: aor.to_string(),
+            contact: Some(contact.to_string()),
+            source_ip: None,
+            user_agent: None,
+            expires,
+            replaced_existing: event_type == RegistrationEventType::Refreshed,
+            timestamp: Utc::now(),
+            churn: None,
+        }
+    }
+
+    pub fn with_source_ip(mut self, source_ip: Option<String>) -> Self {
+        self.source_ip = source_ip;
+        self
+    }
+
+    pub fn with_user_agent(mut self, user_agent: Option<String>) -> Self {
+        self.user_agent = user_agent;
+        self
+    }
+}
+
+/// Churn detection thresholds
+#[derive(Debug, Clone)]
+pub struct ChurnConfig {
+    /// Registrations allowed per AoR within `window` before warning
+    pub max_registrations: usize,
+    /// Sliding window for counting registrations
+    pub window: Duration,
+    /// Assumed NAT binding timeout (seconds) for the short-expires diagnosis
+    pub nat_timeout_secs: u32,
+}
+
+impl Default for ChurnConfig {
+    fn default() -> Self {
+        Self {
+            max_registrations: 10,
+            window: Duration::minutes(5),
+            nat_timeout_secs: 120,
+        }
+    }
+}
+
+#[derive(Default)]
+struct AorState {
+    history: VecDeque<RegistrationEvent>,
+    /// Register/unregister events inside the churn window
+    recent: VecDeque<RegistrationEvent>,
+    last_churn_warning: Option<DateTime<Utc>>,
+}
+
+/// Event log shared by the registrar
+pub struct RegistrationEventLog {
+    config: ChurnConfig,
+    aors: Mutex<HashMap<String, AorState>>,
+    tx: broadcast::Sender<RegistrationEvent>,
+}
+
+impl RegistrationEventLog {
+    pub fn new(config: ChurnConfig) -> Self {
+        let (tx, _) = broadcast::channel(1000);
+        Self {
+            config,
+            aors: Mutex::new(HashMap::new()),
+            tx,
+        }
+    }
+
+    /// Subscribe to registrar events (including churn warnings)
+    pub fn subscribe(&self) -> broadcast::Receiver<RegistrationEvent> {
+        self.tx.subscribe()
+    }
+
+    /// Record an event, returning a churn warning if one was raised
+    pub fn record(&self, event: RegistrationEvent) -> Option<RegistrationEvent> {
+        let churn = {
+            let mut aors = self.aors.lock().unwrap();
+            let state = aors.entry(event.aor.clone()).or_default();
+            Self::push_history(state, event.clone());
+
+            if event.event_type == RegistrationEventType::Expired {
+                None
+            } else {
+                let churn = self.detect_churn(state, &event);
+                if let Some(warning) = &churn {
+                    Self::push_history(state, warning.clone());
+                }
+                churn
+            }
+        };
+
+        self.emit(event);
+        if let Some(warning) = &churn {
+            warn!(
+                "Registration churn for {}: {}",
+                warning.aor,
+                warning
+                    .churn
+                    .as_ref()
+                    .map(|c| c.describe())
+                    .unwrap_or_default()
+            );
+            self.emit(warning.clone());
+        }
+        churn
+    }
+
+    /// Last events for an AoR, oldest first
+    pub fn history(&self, aor: &str) -> Vec<RegistrationEvent> {
+        self.aors
+            .lock()
+            .unwrap()
+            .get(aor)
+            .map(|state| state.history.iter().cloned().collect())
+            .unwrap_or_default()
+    }
+
+    /// Last events for every AoR whose user part is `username`, oldest first
+    pub fn history_for_user(&self, username: &str) -> Vec<RegistrationEvent> {
+        let aors = self.aors.lock().unwrap();
+        let mut events: Vec<RegistrationEvent> = aors
+            .iter()
+            .filter(|(aor, _)| aor_user(aor) == Some(username))
+            .flat_map(|(_, state)| state.history.iter().cloned())
+            .collect();
+        events.sort_by_key(|e| e.timestamp);
+        events
+    }
+
+    fn emit(&self, event: RegistrationEvent) {
+        counter!("registration_events_total", "type" => event.event_type.as_str()).increment(1);
+        // No subscribers is fine
+        let _ = self.tx.send(event);
+    }
+
+    fn push_history(state: &mut AorState, event: RegistrationEvent) {
+        if state.history.len() >= REGISTRATION_HISTORY_SIZE {
+            state.history.pop_front();
+        }
+        state.history.push_back(event);
+    }
+
+    fn detect_churn(
+        &self,
+        state: &mut AorState,
+        event: &RegistrationEvent,
+    ) -> Option<RegistrationEvent> {
+        let window_start = event.timestamp - self.config.window;
+        state.recent.retain(|e| e.timestamp > window_start);
+        state.recent.push_back(event.clone());
+
+        if state.recent.len() <= self.config.max_registrations {
+            return None;
+        }
+        // One warning per window
+        if matches!(state.last_churn_warning, Some(at) if at > window_start) {
+            return None;
+        }
+        state.last_churn_warning = Some(event.timestamp);
+
+        Some(RegistrationEvent {
+            event_type: RegistrationEventType::Churn,
+            aor: event.aor.clone(),
+            contact: event.contact.clone(),
+            source_ip: event.source_ip.clone(),
+            user_agent: event.user_agent.clone(),
+            expires: event.expires,
+            replaced_existing: false,
+            timestamp: event.timestamp,
+            churn: Some(self.diagnose(&state.recent)),
+        })
+    }
+
+    fn diagnose(&self, recent: &VecDeque<RegistrationEvent>) -> ChurnCause {
+        let mut user_agents: Vec<String> = Vec::new();
+        let mut source_ips: Vec<String> = Vec::new();
+        let mut seen = HashSet::new();
+        for event in recent {
+            let device = (event.user_agent.clone(), event.source_ip.clone());
+            if seen.insert(device) {
+                if let Some(ua) = &event.user_agent {
+                    if !user_agents.contains(ua) {
+                        user_agents.push(ua.clone());
+                    }
+                }
+                if let Some(ip) = &event.source_ip {
+                    if !source_ips.contains(ip) {
+                        source_ips.push(ip.clone());
+                    }
+                }
+            }
+        }
+
+        if seen.len() > 1 {
+            return ChurnCause::CompetingDevices {
+                user_agents,
+                source_ips,
+            };
+        }
+
+        let shortest = recent
+            .iter()
+            .filter(|e| e.expires > 0)
+            .map(|e| e.expires)
+            .min();
+        match shortest {
+            Some(expires) if expires < self.config.nat_timeout_secs => ChurnCause::ShortExpires {
+                expires,
+                nat_timeout: self.config.nat_timeout_secs,
+            },
+            _ => ChurnCause::Unknown,
+        }
+    }
+}
+
+impl Default for RegistrationEventLog {
+    fn default() -> Self {
+        Self::new(ChurnConfig::default())
+    }
+}
+
+/// User part of an AoR (`sip:alice@example.com` -> `alice`)
+pub fn aor_user(aor: &str) -> Option<&str> {
+    let rest = aor
+        .strip_prefix("sips:")
+        .or_else(|| aor.strip_prefix("sip:"))
+        .unwrap_or(aor);
+    rest.split('@').next().filter(|_| rest.contains('@'))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn event(kind: RegistrationEventType, contact: &str, ua: &str, ip: &str, expires: u32) -> RegistrationEvent {
+        RegistrationEvent::binding(kind, "sip:alice@example.com", contact, expires)
+            .with_user_agent(Some(ua.to_string()))
+            .with_source_ip(Some(ip.to_string()))
+    }
+
+    #[test]
+    fn test_history_is_bounded() {
+        let log = RegistrationEventLog::default();
+        for _ in 0..(REGISTRATION_HISTORY_SIZE + 5) {
+            log.record(event(RegistrationEventType::Expired, "sip:alice@10.0.0.1", "Phone", "10.0.0.1", 0));
+        }
+        assert_eq!(log.history("sip:alice@example.com").len(), REGISTRATION_HISTORY_SIZE);
+        assert_eq!(log.history_for_user("alice").len(), REGISTRATION_HISTORY_SIZE);
+        assert!(log.history_for_user("bob").is_empty());
+    }
+
+    #[test]
+    fn test_short_expires_diagnosis() {
+        let log = RegistrationEventLog::new(ChurnConfig {
+            max_registrations: 3,
+            ..Default::default()
+        });
+
+        let mut warnings = Vec::new();
+        for _ in 0..6 {
+            warnings.extend(log.record(event(
+                RegistrationEventType::Refreshed,
+                "sip:alice@10.0.0.1",
+                "Phone",
+                "10.0.0.1",
+                60,
+            )));
+        }
+
+        // Only one warning per window
+        assert_eq!(warnings.len(), 1);
+        assert_eq!(
+            warnings[0].churn,
+            Some(ChurnCause::ShortExpires {
+                expires: 60,
+                nat_timeout: 120
+            })
+        );
+    }
+
+    #[test]
+    fn test_aor_user() {
+        assert_eq!(aor_user("sip:alice@example.com"), Some("alice"));
+        assert_eq!(aor_user("sips:bob@example.com:5061"), Some("bob"));
+        assert_eq!(aor_user("example.com"), None);
+    }
+}
@@ -0,0 +1,190 @@
+//! Call takeover with INVITE/Replaces (RFC 3891)
+//!
+//! Outside attended transfer, an INVITE carrying a Replaces header lets
+//! another device seize a call: a user's second phone picks up the call
+//! running on their desk phone (shared line), or an attendant console grabs
+//! a call still ringing elsewhere (which then works as a pickup). The
+//! device must belong to the same user as the leg it replaces, unless the
+//! [`TakeoverPolicy`] lets its user take over calls of that leg's user. The
+//! far end stays connected; the replaced device gets a BYE, or a CANCEL
+//! when it was only ringing.
+
+use super::message::{SipError, SipMethod};
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::net::SocketAddr;
+
+/// Parsed Replaces header: the dialog to replace
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Replaces {
+    pub call_id: String,
+    pub to_tag: Option<String>,
+    pub from_tag: Option<String>,
+    /// Only replace the dialog while it is still early
+    pub early_only: bool,
+}
+
+impl Replaces {
+    /// Parse `call-id;to-tag=...;from-tag=...[;early-only]`
+    pub fn parse(value: &str) -> Option<Self> {
+        let mut parts = value.split(';').map(str::trim);
+        let call_id = parts.next().filter(|id| !id.is_empty())?.to_string();
+        let mut replaces = Self {
+            call_id,
+            to_tag: None,
+            from_tag: None,
+            early_only: false,
+        };
+        for param in parts {
+            let (name, value) = param.split_once('=').unwrap_or((param, ""));
+            match name.trim().to_ascii_lowercase().as_str() {
+                "to-tag" => replaces.to_tag = Some(value.trim().to_string()),
+                "from-tag" => replaces.from_tag = Some(value.trim().to_string()),
+                "early-only" => replaces.early_only = true,
+                _ => {}
+            }
+        }
+        Some(replaces)
+    }
+
+    /// Whether the tags match the dialog tagged `tag_a` and `tag_b`, in
+    /// either order; tags unknown on either side are not compared
+    pub fn matches_tags(&self, tag_a: Option<&str>, tag_b: Option<&str>) -> bool {
+        let same = |ours: &Option<String>, theirs: Option<&str>| match (ours, theirs) {
+            (Some(ours), Some(theirs)) => ours == theirs,
+            _ => true,
+        };
+        (same(&self.to_tag, tag_a) && same(&self.from_tag, tag_b))
+            || (same(&self.to_tag, tag_b) && same(&self.from_tag, tag_a))
+    }
+}
+
+/// Who may take over whose calls
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct TakeoverPolicy {
+    /// Let a user's other devices take over their calls (shared line)
+    pub same_user: bool,
+    /// Users allowed to take over calls of other users, by username, e.g.
+    /// `reception = ["*"]` for an attendant console
+    pub permissions: HashMap<String, Vec<String>>,
+}
+
+impl Default for TakeoverPolicy {
+    fn default() -> Self {
+        Self {
+            same_user: true,
+            permissions: HashMap::new(),
+        }
+    }
+}
+
+impl TakeoverPolicy {
+    /// Whether `requester` may take over a call leg of `owner` (usernames)
+    pub fn permits(&self, requester: &str, owner: &str) -> bool {
+        (self.same_user && requester == owner)
+            || self
+                .permissions
+                .get(requester)
+                .is_some_and(|owners| owners.iter().any(|o| o == "*" || o == owner))
+    }
+}
+
+/// Why a takeover was refused
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TakeoverError {
+    /// No active call matches the Replaces header
+    NoDialog,
+    /// The requester may not take over the call
+    Forbidden,
+    /// `early-only` but the call was already answered
+    AlreadyAnswered,
+}
+
+impl TakeoverError {
+    /// Final response to the INVITE
+    pub fn status_code(&self) -> u16 {
+        match self {
+            TakeoverError::NoDialog => 481,
+            TakeoverError::Forbidden => 403,
+            TakeoverError::AlreadyAnswered => 486,
+        }
+    }
+}
+
+impl std::fmt::Display for TakeoverError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            TakeoverError::NoDialog => write!(f, "no matching call"),
+            TakeoverError::Forbidden => write!(f, "takeover not permitted"),
+            TakeoverError::AlreadyAnswered => write!(f, "early-only call already answered"),
+        }
+    }
+}
+
+/// The device a takeover replaced
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ReplacedLeg {
+    pub uri: String,
+    pub contact: Option<SocketAddr>,
+    /// The leg was answered; otherwise it was still ringing
+    pub confirmed: bool,
+}
+
+impl ReplacedLeg {
+    /// Request ending the replaced device's dialog
+    pub fn release_method(&self) -> SipMethod {
+        if self.confirmed {
+            SipMethod::Bye
+        } else {
+            SipMethod::Cancel
+        }
+    }
+}
+
+/// Ends the dialog of a replaced device
+#[async_trait]
+pub trait LegReleaser: Send + Sync {
+    /// Send `leg.release_method()` to the replaced device
+    async fn release(&self, call_id: &str, leg: &ReplacedLeg) -> Result<(), SipError>;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_replaces() {
+        let replaces = Replaces::parse("abc@host;to-tag=7743;from-tag=6472;early-only").unwrap();
+        assert_eq!(replaces.call_id, "abc@host");
+        assert_eq!(replaces.to_tag.as_deref(), Some("7743"));
+        assert_eq!(replaces.from_tag.as_deref(), Some("6472"));
+        assert!(replaces.early_only);
+
+        assert!(replaces.matches_tags(Some("6472"), Some("7743")));
+        assert!(replaces.matches_tags(Some("7743"), None));
+        assert!(!replaces.matches_tags(Some("6472"), Some("other")));
+        assert!(Replaces::parse(";to-tag=1").is_none());
+    }
+
+    #[test]
+    fn test_takeover_permissions() {
+        let mut policy = TakeoverPolicy::default();
+        policy
+            .permissions
+            .insert("reception".to_string(), vec!["*".to_string()]);
+        policy
+            .permissions
+            .insert("assistant".to_string(), vec!["ceo".to_string()]);
+
+        assert!(policy.permits("bob", "bob"));
+        assert!(policy.permits("reception", "bob"));
+        assert!(policy.permits("assistant", "ceo"));
+        assert!(!policy.permits("assistant", "bob"));
+        assert!(!policy.permits("carol", "bob"));
+
+        policy.same_user = false;
+        assert!(!policy.permits("bob", "bob"));
+    }
+}
@@ -0,0 +1,1079 @@
+//! RFC 3263 resolution of SIP destinations
+//!
+//! A SIP URI names a domain rather than a server: NAPTR records of the
+//! domain pick the transport, SRV records of the chosen service give hosts
+//! and ports with priorities and weights, and A/AAAA records the addresses.
+//! An IP literal, explicit port or `transport` parameter skips the steps it
+//! makes unnecessary; a domain without SRV records is used with the
+//! transport's default port.
+//!
+//! [`SipResolver`] returns every target of a destination in the order it
+//! should be tried. Answers are cached for their TTL. A target that failed
+//! (unreachable, transaction timeout, 503) is put last for `blacklist_secs`,
+//! so retries go to the next target instead of hammering a dead host.
+//! [`FailoverForwarder`] forwards INVITEs to the targets in turn.
+
+use super::address::{format_host_port, DEFAULT_SIP_PORT};
+use super::message::{SipError, SipRequest, SipResponse};
+use super::redirect::InviteForwarder;
+use super::transport::TransportProtocol;
+use crate::infrastructure::clock::{system_clock, Clock};
+use async_trait::async_trait;
+use hickory_resolver::error::ResolveErrorKind;
+use hickory_resolver::proto::rr::{RData, RecordType};
+use hickory_resolver::TokioAsyncResolver;
+use metrics::counter;
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fmt;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::sync::mpsc;
+use tokio::time::Instant;
+use tracing::{debug, info, warn};
+
+/// Default port of SIP over TLS
+pub const DEFAULT_SIPS_PORT: u16 = 5061;
+
+/// DNS resolution of SIP destinations
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SipResolverConfig {
+    /// Resolve trunk and external destinations through NAPTR/SRV; when
+    /// off, hosts are looked up as plain names
+    pub enabled: bool,
+    /// Transports offered to NAPTR and SRV records, in order of preference
+    pub transports: Vec<TransportProtocol>,
+    /// Shortest time an answer is cached, whatever its TTL
+    pub min_ttl_secs: u64,
+    /// Longest time an answer is cached, whatever its TTL
+    pub max_ttl_secs: u64,
+    /// How long a name without records is remembered as such
+    pub negative_ttl_secs: u64,
+    /// How long a failed target is tried last
+    pub blacklist_secs: u64,
+    /// Most cached answers per record type
+    pub max_cache_entries: usize,
+}
+
+impl Default for SipResolverConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            transports: vec![
+                TransportProtocol::Udp,
+                TransportProtocol::Tcp,
+                TransportProtocol::Tls,
+            ],
+            min_ttl_secs: 5,
+            max_ttl_secs: 3600,
+            negative_ttl_secs: 30,
+            blacklist_secs: 30,
+            max_cache_entries: 10_000,
+        }
+    }
+}
+
+impl SipResolverConfig {
+    pub fn validate(&self) -> Result<(), String> {
+        if self.transports.is_empty() {
+            return Err("at least one transport is required".to_string());
+        }
+        if let Some(transport) = self.transports.iter().find(|t| {
+            !matches!(
+                t,
+                TransportProtocol::Udp | TransportProtocol::Tcp | TransportProtocol::Tls
+            )
+        }) {
+            return Err(format!("{} has no NAPTR/SRV service", transport.as_str()));
+        }
+        if self.min_ttl_secs > self.max_ttl_secs {
+            return Err("min_ttl_secs must not exceed max_ttl_secs".to_string());
+        }
+        if self.max_cache_entries == 0 {
+            return Err("max_cache_entries must be at least 1".to_string());
+        }
+        Ok(())
+    }
+}
+
+/// NAPTR record (RFC 3403)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct NaptrRecord {
+    pub order: u16,
+    pub preference: u16,
+    pub flags: String,
+    /// e.g. `SIP+D2U`
+    pub services: String,
+    /// SRV name the record points to
+    pub replacement: String,
+}
+
+/// SRV record (RFC 2782)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SrvRecord {
+    pub priority: u16,
+    pub weight: u16,
+    pub port: u16,
+    /// Host name; `.` means the service is not offered
+    pub target: String,
+}
+
+/// Records of one query and how long they may be cached
+#[derive(Debug, Clone)]
+pub struct DnsAnswer<T> {
+    pub records: Vec<T>,
+    pub ttl: Duration,
+}
+
+impl<T> DnsAnswer<T> {
+    pub fn new(records: Vec<T>, ttl: Duration) -> Self {
+        Self { records, ttl }
+    }
+}
+
+/// DNS queries [`SipResolver`] needs
+///
+/// A name without records of the type is an empty answer, not an error;
+/// errors are failures to get an answer at all.
+#[async_trait]
+pub trait DnsLookup: Send + Sync {
+    async fn naptr(&self, domain: &str) -> Result<DnsAnswer<NaptrRecord>, String>;
+
+    async fn srv(&self, name: &str) -> Result<DnsAnswer<SrvRecord>, String>;
+
+    /// A and AAAA records of `host`
+    async fn ip(&self, host: &str) -> Result<DnsAnswer<IpAddr>, String>;
+}
+
+/// DNS queries through the system's name servers (and hosts file)
+pub struct HickoryLookup {
+    resolver: TokioAsyncResolver,
+}
+
+impl HickoryLookup {
+    pub fn from_system_conf() -> Result<Self, String> {
+        TokioAsyncResolver::tokio_from_system_conf()
+            .map(|resolver| Self { resolver })
+            .map_err(|e| format!("Failed to read the system DNS configuration: {}", e))
+    }
+}
+
+/// Names are queried fully qualified, so the search list is not tried
+fn fqdn(name: &str) -> String {
+    if name.ends_with('.') {
+        name.to_string()
+    } else {
+        format!("{}.", name)
+    }
+}
+
+fn name_of(name: &impl fmt::Display) -> String {
+    name.to_string().trim_end_matches('.').to_lowercase()
+}
+
+fn until(valid_until: std::time::Instant) -> Duration {
+    valid_until.saturating_duration_since(std::time::Instant::now())
+}
+
+fn answer<L, T>(
+    result: Result<L, hickory_resolver::error::ResolveError>,
+    records: impl FnOnce(&L) -> (Vec<T>, Duration),
+) -> Result<DnsAnswer<T>, String> {
+    match result {
+        Ok(lookup) => {
+            let (records, ttl) = records(&lookup);
+            Ok(DnsAnswer::new(records, ttl))
+        }
+        Err(e) => match e.kind() {
+            ResolveErrorKind::NoRecordsFound { negative_ttl, .. } => Ok(DnsAnswer::new(
+                Vec::new(),
+                Duration::from_secs(negative_ttl.unwrap_or(0) as u64),
+            )),
+            _ => Err(e.to_string()),
+        },
+    }
+}
+
+#[async_trait]
+impl DnsLookup for HickoryLookup {
+    async fn naptr(&self, domain: &str) -> Result<DnsAnswer<NaptrRecord>, String> {
+        let result = self.resolver.lookup(fqdn(domain), RecordType::NAPTR).await;
+        answer(result, |lookup| {
+            let records = lookup
+                .iter()
+                .filter_map(|data| match data {
+                    RData::NAPTR(naptr) => Some(NaptrRecord {
+                        order: naptr.order(),
+                        preference: naptr.preference(),
+                        flags: String::from_utf8_lossy(naptr.flags()).to_string(),
+                        services: String::from_utf8_lossy(naptr.services()).to_string(),
+                        replacement: name_of(naptr.replacement()),
+                    }),
+                    _ => None,
+                })
+                .collect();
+            (records, until(lookup.valid_until()))
+        })
+    }
+
+    async fn srv(&self, name: &str) -> Result<DnsAnswer<SrvRecord>, String> {
+        let result = self.resolver.srv_lookup(fqdn(name)).await;
+        answer(result, |lookup| {
+            let records = lookup
+                .iter()
+                .map(|srv| SrvRecord {
+                    priority: srv.priority(),
+                    weight: srv.weight(),
+                    port: srv.port(),
+                    target: match name_of(srv.target()) {
+                        target if target.is_empty() => ".".to_string(),
+                        target => target,
+                    },
+                })
+                .collect();
+            (records, until(lookup.as_lookup().valid_until()))
+        })
+    }
+
+    async fn ip(&self, host: &str) -> Result<DnsAnswer<IpAddr>, String> {
+        let result = self.resolver.lookup_ip(fqdn(host)).await;
+        answer(result, |lookup| {
+            (lookup.iter().collect(), until(lookup.valid_until()))
+        })
+    }
+}
+
+/// Where to send a request
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize)]
+pub struct SipTarget {
+    /// Name the address was found for (the TLS server name)
+    pub host: String,
+    pub addr: SocketAddr,
+    pub transport: TransportProtocol,
+}
+
+impl fmt::Display for SipTarget {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} {} ({})",
+            self.transport.as_str(),
+            format_host_port(self.addr),
+            self.host
+        )
+    }
+}
+
+/// Parts of a SIP URI that decide how it is resolved
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Destination {
+    secure: bool,
+    host: String,
+    port: Option<u16>,
+    transport: Option<TransportProtocol>,
+}
+
+impl Destination {
+    fn parse(uri: &str) -> Result<Self, String> {
+        let uri = uri.trim();
+        let (secure, rest) = match uri.strip_prefix("sips:") {
+            Some(rest) => (true, rest),
+            None => (false, uri.strip_prefix("sip:").unwrap_or(uri)),
+        };
+        let rest = rest.split('?').next().unwrap_or_default();
+        let mut parts = rest.split(';');
+        let address = parts.next().unwrap_or_default();
+        let host_port = address.rsplit_once('@').map_or(address, |(_, host)| host);
+
+        let (host, port) = if let Some(bracketed) = host_port.strip_prefix('[') {
+            let (host, after) = bracketed
+                .split_once(']')
+                .ok_or_else(|| format!("Invalid host in {}", uri))?;
+            (host, after.strip_prefix(':'))
+        } else {
+            match host_port.split_once(':') {
+                Some((host, port)) => (host, Some(port)),
+                None => (host_port, None),
+            }
+        };
+        if host.is_empty() {
+            return Err(format!("No host in {}", uri));
+        }
+        let port = port
+            .map(|port| {
+                port.parse::<u16>()
+                    .map_err(|_| format!("Invalid port in {}", uri))
+            })
+            .transpose()?;
+
+        let transport = parts
+            .filter_map(|param| param.split_once('='))
+            .find(|(name, _)| name.eq_ignore_ascii_case("transport"))
+            .map(|(_, value)| match value.to_ascii_lowercase().as_str() {
+                "udp" => Ok(TransportProtocol::Udp),
+                "tcp" if secure => Ok(TransportProtocol::Tls),
+                "tcp" => Ok(TransportProtocol::Tcp),
+                "tls" => Ok(TransportProtocol::Tls),
+                other => Err(format!("Unsupported transport {} in {}", other, uri)),
+            })
+            .transpose()?;
+
+        Ok(Self {
+            secure,
+            host: host.to_lowercase(),
+            port,
+            transport,
+        })
+    }
+
+    fn default_transport(&self) -> TransportProtocol {
+        if self.secure {
+            TransportProtocol::Tls
+        } else {
+            TransportProtocol::Udp
+        }
+    }
+}
+
+fn default_port(transport: TransportProtocol) -> u16 {
+    match transport {
+        TransportProtocol::Tls | TransportProtocol::Wss => DEFAULT_SIPS_PORT,
+        _ => DEFAULT_SIP_PORT,
+    }
+}
+
+/// NAPTR service of a transport
+fn naptr_service(transport: TransportProtocol) -> Option<&'static str> {
+    match transport {
+        TransportProtocol::Udp => Some("SIP+D2U"),
+        TransportProtocol::Tcp => Some("SIP+D2T"),
+        TransportProtocol::Tls => Some("SIPS+D2T"),
+        _ => None,
+    }
+}
+
+/// SRV name of a transport's service in `domain`
+fn srv_name(transport: TransportProtocol, domain: &str) -> String {
+    match transport {
+        TransportProtocol::Tls => format!("_sips._tcp.{}", domain),
+        TransportProtocol::Tcp => format!("_sip._tcp.{}", domain),
+        _ => format!("_sip._udp.{}", domain),
+    }
+}
+
+/// Order SRV records for trying: by priority, and within a priority by a
+/// weighted random draw (RFC 2782)
+fn order_srv(mut records: Vec<SrvRecord>, rng: &mut impl Rng) -> Vec<SrvRecord> {
+    // Zero weights first, so they are only drawn when the draw is 0
+    records.sort_by_key(|record| (record.priority, record.weight != 0));
+    let mut ordered = Vec::with_capacity(records.len());
+    let mut rest = records.as_slice();
+    while let Some(first) = rest.first() {
+        let same = rest
+            .iter()
+            .position(|record| record.priority != first.priority)
+            .unwrap_or(rest.len());
+        let mut group = rest[..same].to_vec();
+        rest = &rest[same..];
+        while !group.is_empty() {
+            let total: u32 = group.iter().map(|record| record.weight as u32).sum();
+            let draw = rng.gen_range(0..=total);
+            let mut running = 0;
+            let chosen = group
+                .iter()
+                .position(|record| {
+                    running += record.weight as u32;
+                    running >= draw
+                })
+                .unwrap_or(0);
+            ordered.push(group.remove(chosen));
+        }
+    }
+    ordered
+}
+
+/// Answers of one record type, kept until they expire
+struct TtlCache<T> {
+    entries: Mutex<HashMap<String, (Instant, Vec<T>)>>,
+    capacity: usize,
+}
+
+impl<T: Clone> TtlCache<T> {
+    fn new(capacity: usize) -> Self {
+        Self {
+            entries: Mutex::new(HashMap::new()),
+            capacity,
+        }
+    }
+
+    fn get(&self, name: &str, now: Instant) -> Option<Vec<T>> {
+        let entries = self.entries.lock().unwrap();
+        entries
+            .get(name)
+            .filter(|(expires, _)| *expires > now)
+            .map(|(_, records)| records.clone())
+    }
+
+    fn insert(&self, name: &str, records: Vec<T>, expires: Instant, now: Instant) {
+        let mut entries = self.entries.lock().unwrap();
+        if entries.len() >= self.capacity && !entries.contains_key(name) {
+            entries.retain(|_, (expires, _)| *expires > now);
+            if entries.len() >= self.capacity {
+                let soonest = entries
+                    .iter()
+                    .min_by_key(|(_, (expires, _))| *expires)
+                    .map(|(name, _)| name.clone());
+                if let Some(soonest) = soonest {
+                    entries.remove(&soonest);
+                }
+            }
+        }
+        entries.insert(name.to_string(), (expires, records));
+    }
+
+    fn clear(&self) {
+        self.entries.lock().unwrap().clear();
+    }
+}
+
+/// Resolves SIP URIs to the ordered targets to send requests to
+pub struct SipResolver {
+    config: SipResolverConfig,
+    dns: Arc<dyn DnsLookup>,
+    naptr: TtlCache<NaptrRecord>,
+    srv: TtlCache<SrvRecord>,
+    ip: TtlCache<IpAddr>,
+    /// Failed targets and until when they are tried last
+    blacklist: Mutex<HashMap<(SocketAddr, TransportProtocol), Instant>>,
+    rng: Mutex<StdRng>,
+    clock: Arc<dyn Clock>,
+}
+
+impl SipResolver {
+    pub fn new(config: SipResolverConfig, dns: Arc<dyn DnsLookup>) -> Self {
+        let capacity = config.max_cache_entries.max(1);
+        Self {
+            config,
+            dns,
+            naptr: TtlCache::new(capacity),
+            srv: TtlCache::new(capacity),
+            ip: TtlCache::new(capacity),
+            blacklist: Mutex::new(HashMap::new()),
+            rng: Mutex::new(StdRng::from_entropy()),
+            clock: system_clock(),
+        }
+    }
+
+    /// Time cache entries and the blacklist by `clock`
+    pub fn with_clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    /// Draw SRV weights from a seeded generator, for repeatable orders
+    pub fn with_seed(mut self, seed: u64) -> Self {
+        self.rng = Mutex::new(StdRng::seed_from_u64(seed));
+        self
+    }
+
+    /// Targets of `uri` in the order they should be tried; recently failed
+    /// targets come last
+    pub async fn resolve(&self, uri: &str) -> Result<Vec<SipTarget>, String> {
+        let destination = Destination::parse(uri)?;
+        let mut targets = self.lookup_targets(&destination).await?;
+        if targets.is_empty() {
+            return Err(format!("{} has no SIP servers", destination.host));
+        }
+        let mut seen = std::collections::HashSet::new();
+        targets.retain(|target| seen.insert((target.addr, target.transport)));
+
+        let now = self.clock.instant();
+        let mut blacklist = self.blacklist.lock().unwrap();
+        blacklist.retain(|_, until| *until > now);
+        // Stable: the order among failed and among working targets is kept
+        targets.sort_by_key(|target| blacklist.contains_key(&(target.addr, target.transport)));
+        debug!(
+            "{} resolves to {}",
+            uri,
+            targets
+                .iter()
+                .map(ToString::to_string)
+                .collect::<Vec<_>>()
+                .join(", ")
+        );
+        Ok(targets)
+    }
+
+    /// `target` did not answer: try it last for `blacklist_secs`
+    pub fn mark_failed(&self, target: &SipTarget) {
+        let until = self.clock.instant() + Duration::from_secs(self.config.blacklist_secs);
+        let previous = self
+            .blacklist
+            .lock()
+            .unwrap()
+            .insert((target.addr, target.transport), until);
+        if previous.is_none() {
+            warn!(
+                "SIP target {} failed; trying it last for {}s",
+                target, self.config.blacklist_secs
+            );
+        }
+        counter!("sip_dns_target_failures_total").increment(1);
+    }
+
+    /// `target` answered: it is tried in its normal place again
+    pub fn mark_succeeded(&self, target: &SipTarget) {
+        if self
+            .blacklist
+            .lock()
+            .unwrap()
+            .remove(&(target.addr, target.transport))
+            .is_some()
+        {
+            info!("SIP target {} answering again", target);
+        }
+    }
+
+    /// Drop every cached answer, e.g. after the DNS zone changed
+    pub fn flush(&self) {
+        self.naptr.clear();
+        self.srv.clear();
+        self.ip.clear();
+    }
+
+    /// Transports the destination may use, in order of preference
+    fn transports(&self, destination: &Destination) -> Vec<TransportProtocol> {
+        self.config
+            .transports
+            .iter()
+            .copied()
+            .filter(|transport| !destination.secure || *transport == TransportProtocol::Tls)
+            .filter(|transport| naptr_service(*transport).is_some())
+            .collect()
+    }
+
+    async fn lookup_targets(&self, destination: &Destination) -> Result<Vec<SipTarget>, String> {
+        let host = &destination.host;
+        let transport = destination
+            .transport
+            .unwrap_or_else(|| destination.default_transport());
+
+        if let Ok(ip) = host.parse::<IpAddr>() {
+            let port = destination.port.unwrap_or_else(|| default_port(transport));
+            return Ok(vec![SipTarget {
+                host: host.clone(),
+                addr: SocketAddr::new(ip, port),
+                transport,
+            }]);
+        }
+        if let Some(port) = destination.port {
+            return self.addresses(host, port, transport).await;
+        }
+
+        let services = match destination.transport {
+            Some(transport) => vec![(transport, srv_name(transport, host))],
+            None => {
+                let services = self.naptr_services(destination).await?;
+                if services.is_empty() {
+                    self.transports(destination)
+                        .into_iter()
+                        .map(|transport| (transport, srv_name(transport, host)))
+                        .collect()
+                } else {
+                    services
+                }
+            }
+        };
+
+        let mut targets = Vec::new();
+        let mut any_srv = false;
+        for (transport, name) in services {
+            let records = self.cached_srv(&name).await?;
+            any_srv |= !records.is_empty();
+            let records = order_srv(records, &mut *self.rng.lock().unwrap());
+            for record in records {
+                if record.target == "." {
+                    continue;
+                }
+                match self.addresses(&record.target, record.port, transport).await {
+                    Ok(addresses) => targets.extend(addresses),
+                    Err(e) => warn!("Skipping SRV target {} of {}: {}", record.target, name, e),
+                }
+            }
+        }
+        if !any_srv {
+            return self
+                .addresses(host, default_port(transport), transport)
+                .await;
+        }
+        Ok(targets)
+    }
+
+    /// SRV names of the NAPTR records of the destination's domain, in
+    /// order, for transports we offer
+    async fn naptr_services(
+        &self,
+        destination: &Destination,
+    ) -> Result<Vec<(TransportProtocol, String)>, String> {
+        let transports = self.transports(destination);
+        let mut records = match self.naptr.get(&destination.host, self.clock.instant()) {
+            Some(records) => records,
+            None => {
+                counter!("sip_dns_queries_total", "type" => "NAPTR").increment(1);
+                let answer = self.dns.naptr(&destination.host).await?;
+                self.remember(&self.naptr, &destination.host, answer)
+            }
+        };
+        records.sort_by_key(|record| (record.order, record.preference));
+        Ok(records
+            .into_iter()
+            .filter(|record| record.flags.eq_ignore_ascii_case("s"))
+            .filter_map(|record| {
+                let transport = transports.iter().copied().find(|transport| {
+                    naptr_service(*transport)
+                        .is_some_and(|service| record.services.eq_ignore_ascii_case(service))
+                })?;
+                Some((transport, record.replacement))
+            })
+            .collect())
+    }
+
+    async fn cached_srv(&self, name: &str) -> Result<Vec<SrvRecord>, String> {
+        if let Some(records) = self.srv.get(name, self.clock.instant()) {
+            return Ok(records);
+        }
+        counter!("sip_dns_queries_total", "type" => "SRV").increment(1);
+        let answer = self.dns.srv(name).await?;
+        Ok(self.remember(&self.srv, name, answer))
+    }
+
+    async fn addresses(
+        &self,
+        host: &str,
+        port: u16,
+        transport: TransportProtocol,
+    ) -> Result<Vec<SipTarget>, String> {
+        let ips = match host.parse::<IpAddr>() {
+            Ok(ip) => vec![ip],
+            Err(_) => match self.ip.get(host, self.clock.instant()) {
+                Some(ips) => ips,
+                None => {
+                    counter!("sip_dns_queries_total", "type" => "A").increment(1);
+                    let answer = self.dns.ip(host).await?;
+                    self.remember(&self.ip, host, answer)
+                }
+            },
+        };
+        Ok(ips
+            .into_iter()
+            .map(|ip| SipTarget {
+                host: host.to_string(),
+                addr: SocketAddr::new(ip, port),
+                transport,
+            })
+            .collect())
+    }
+
+    /// Cache an answer for its TTL, clamped to the configured bounds
+    fn remember<T: Clone>(&self, cache: &TtlCache<T>, name: &str, answer: DnsAnswer<T>) -> Vec<T> {
+        let secs = if answer.records.is_empty() {
+            self.config.negative_ttl_secs
+        } else {
+            answer
+                .ttl
+                .as_secs()
+                .clamp(self.config.min_ttl_secs, self.config.max_ttl_secs)
+        };
+        let now = self.clock.instant();
+        cache.insert(
+            name,
+            answer.records.clone(),
+            now + Duration::from_secs(secs),
+            now,
+        );
+        answer.records
+    }
+}
+
+/// `uri` sent to `target`: its host and port replaced by the target's
+/// address and its transport set
+pub fn target_uri(uri: &str, target: &SipTarget) -> String {
+    let (scheme, rest) = match uri.strip_prefix("sips:") {
+        Some(rest) => ("sips:", rest),
+        None => ("sip:", uri.strip_prefix("sip:").unwrap_or(uri)),
+    };
+    let end = rest.find([';', '?']).unwrap_or(rest.len());
+    let (address, tail) = rest.split_at(end);
+    let user = address.rsplit_once('@').map(|(user, _)| user);
+
+    let (params, headers) = tail.split_once('?').unwrap_or((tail, ""));
+    let mut params: Vec<&str> = params
+        .split(';')
+        .filter(|param| !param.is_empty())
+        .filter(|param| {
+            !param
+                .split('=')
+                .next()
+                .unwrap_or_default()
+                .eq_ignore_ascii_case("transport")
+        })
+        .collect();
+    let transport = format!("transport={}", target.transport.as_str().to_lowercase());
+    params.insert(0, &transport);
+
+    let mut result = String::from(scheme);
+    if let Some(user) = user {
+        result.push_str(user);
+        result.push('@');
+    }
+    result.push_str(&format_host_port(target.addr));
+    for param in params {
+        result.push(';');
+        result.push_str(param);
+    }
+    if !headers.is_empty() {
+        result.push('?');
+        result.push_str(headers);
+    }
+    result
+}
+
+/// Forwards an INVITE to the resolved targets of its destination in turn,
+/// until one answers with anything but 503
+///
+/// Destinations with an IP literal host are forwarded as they are.
+pub struct FailoverForwarder<'a> {
+    inner: &'a dyn InviteForwarder,
+    resolver: &'a SipResolver,
+    served_by: Mutex<Option<SipTarget>>,
+}
+
+impl<'a> FailoverForwarder<'a> {
+    pub fn new(inner: &'a dyn InviteForwarder, resolver: &'a SipResolver) -> Self {
+        Self {
+            inner,
+            resolver,
+            served_by: Mutex::new(None),
+        }
+    }
+
+    /// Target that gave the last final response
+    pub fn served_by(&self) -> Option<SipTarget> {
+        self.served_by.lock().unwrap().clone()
+    }
+
+    async fn forward_to_targets(
+        &self,
+        target: &str,
+        request: &SipRequest,
+        progress: Option<mpsc::UnboundedSender<u16>>,
+    ) -> Result<SipResponse, SipError> {
+        let forward = |destination: String| {
+            let progress = progress.clone();
+            async move {
+                match progress {
+                    Some(progress) => {
+                        self.inner
+                            .forward_with_progress(&destination, request, progress)
+                            .await
+                    }
+                    None => self.inner.forward(&destination, request).await,
+                }
+            }
+        };
+
+        let literal = Destination::parse(target)
+            .is_ok_and(|destination| destination.host.parse::<IpAddr>().is_ok());
+        if literal {
+            return forward(target.to_string()).await;
+        }
+
+        let targets = self
+            .resolver
+            .resolve(target)
+            .await
+            .map_err(SipError::TransportError)?;
+        let mut last = None;
+        for resolved in targets {
+            let result = forward(target_uri(target, &resolved)).await;
+            match &result {
+                Ok(response) if response.status_code() != 503 => {
+                    self.resolver.mark_succeeded(&resolved);
+                    *self.served_by.lock().unwrap() = Some(resolved);
+                    return result;
+                }
+                Ok(_) => warn!("{} of {} is unavailable (503)", resolved, target),
+                Err(e) => warn!("{} of {} unreachable: {}", resolved, target, e),
+            }
+            self.resolver.mark_failed(&resolved);
+            last = Some(result);
+        }
+        last.unwrap_or_else(|| {
+            Err(SipError::TransportError(format!(
+                "{} has no SIP servers",
+                target
+            )))
+        })
+    }
+}
+
+#[async_trait]
+impl InviteForwarder for FailoverForwarder<'_> {
+    async fn forward(&self, target: &str, request: &SipRequest) -> Result<SipResponse, SipError> {
+        self.forward_to_targets(target, request, None).await
+    }
+
+    async fn forward_with_progress(
+        &self,
+        target: &str,
+        request: &SipRequest,
+        progress: mpsc::UnboundedSender<u16>,
+    ) -> Result<SipResponse, SipError> {
+        self.forward_to_targets(target, request, Some(progress))
+            .await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::infrastructure::protocols::sip::builder::ResponseBuilder;
+    use crate::test_support::{ManualClock, StaticDns};
+
+    fn carrier() -> StaticDns {
+        StaticDns::new()
+            .naptr(
+                "carrier.example",
+                &[
+                    (20, "SIP+D2U", "_sip._udp.carrier.example"),
+                    (10, "SIP+D2T", "_sip._tcp.carrier.example"),
+                ],
+            )
+            .srv(
+                "_sip._tcp.carrier.example",
+                &[
+                    (20, 0, 5080, "backup.carrier.example"),
+                    (10, 50, 5060, "a.carrier.example"),
+                    (10, 50, 5060, "b.carrier.example"),
+                ],
+            )
+            .srv(
+                "_sip._udp.carrier.example",
+                &[(10, 0, 5060, "a.carrier.example")],
+            )
+            .host("a.carrier.example", "192.0.2.1")
+            .host("b.carrier.example", "192.0.2.2")
+            .host("backup.carrier.example", "192.0.2.9")
+    }
+
+    fn addrs(targets: &[SipTarget]) -> Vec<String> {
+        targets
+            .iter()
+            .map(|target| format!("{} {}", target.transport.as_str(), target.addr))
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn test_naptr_srv_and_address_order() {
+        let resolver = SipResolver::new(SipResolverConfig::default(), Arc::new(carrier()));
+
+        let targets = resolver
+            .resolve("sip:+14155551234@carrier.example")
+            .await
+            .unwrap();
+        let targets = addrs(&targets);
+        // TCP first (NAPTR order), priority 10 before 20, then UDP
+        assert_eq!(targets.len(), 4);
+        assert!(targets[..2].contains(&"TCP 192.0.2.1:5060".to_string()));
+        assert!(targets[..2].contains(&"TCP 192.0.2.2:5060".to_string()));
+        assert_eq!(targets[2], "TCP 192.0.2.9:5080");
+        assert_eq!(targets[3], "UDP 192.0.2.1:5060");
+
+        // A transport parameter skips NAPTR, an explicit port SRV
+        let udp = resolver
+            .resolve("sip:carrier.example;transport=udp")
+            .await
+            .unwrap();
+        assert_eq!(addrs(&udp), vec!["UDP 192.0.2.1:5060"]);
+        let direct = resolver
+            .resolve("sip:b.carrier.example:5070")
+            .await
+            .unwrap();
+        assert_eq!(addrs(&direct), vec!["UDP 192.0.2.2:5070"]);
+        let literal = resolver.resolve("sips:alice@[2001:db8::1]").await.unwrap();
+        assert_eq!(addrs(&literal), vec!["TLS [2001:db8::1]:5061"]);
+    }
+
+    #[tokio::test]
+    async fn test_domain_without_srv_uses_default_port() {
+        let dns = StaticDns::new().host("pbx.example", "198.51.100.7");
+        let resolver = SipResolver::new(SipResolverConfig::default(), Arc::new(dns));
+
+        let targets = resolver.resolve("sip:pbx.example").await.unwrap();
+        assert_eq!(addrs(&targets), vec!["UDP 198.51.100.7:5060"]);
+        assert!(resolver.resolve("sip:unknown.example").await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_weighted_selection_among_same_priority() {
+        let dns = StaticDns::new()
+            .srv(
+                "_sip._udp.carrier.example",
+                &[
+                    (10, 75, 5060, "a.carrier.example"),
+                    (10, 25, 5060, "b.carrier.example"),
+                    (20, 100, 5060, "backup.carrier.example"),
+                ],
+            )
+            .host("a.carrier.example", "192.0.2.1")
+            .host("b.carrier.example", "192.0.2.2")
+            .host("backup.carrier.example", "192.0.2.9");
+        let resolver = SipResolver::new(SipResolverConfig::default(), Arc::new(dns)).with_seed(7);
+
+        let mut first_a = 0;
+        for _ in 0..2000 {
+            let targets = resolver
+                .resolve("sip:carrier.example;transport=udp")
+                .await
+                .unwrap();
+            assert_eq!(targets.len(), 3);
+            // The higher priority value is always last
+            assert_eq!(targets[2].addr, "192.0.2.9:5060".parse().unwrap());
+            if targets[0].addr == "192.0.2.1:5060".parse().unwrap() {
+                first_a += 1;
+            }
+        }
+        // 75% of the draws, give or take
+        assert!((1400..=1600).contains(&first_a), "{}", first_a);
+    }
+
+    #[tokio::test]
+    async fn test_failed_target_is_tried_last_until_blacklist_expires() {
+        let clock = Arc::new(ManualClock::new());
+        let dns = StaticDns::new()
+            .srv(
+                "_sip._udp.carrier.example",
+                &[
+                    (10, 0, 5060, "a.carrier.example"),
+                    (20, 0, 5060, "b.carrier.example"),
+                ],
+            )
+            .host("a.carrier.example", "192.0.2.1")
+            .host("b.carrier.example", "192.0.2.2");
+        let dns = Arc::new(dns);
+        let resolver =
+            SipResolver::new(SipResolverConfig::default(), dns.clone()).with_clock(clock.clone());
+        let uri = "sip:carrier.example;transport=udp";
+
+        let targets = resolver.resolve(uri).await.unwrap();
+        assert_eq!(
+            addrs(&targets),
+            vec!["UDP 192.0.2.1:5060", "UDP 192.0.2.2:5060"]
+        );
+        let queries = dns.queries();
+
+        resolver.mark_failed(&targets[0]);
+        let targets = resolver.resolve(uri).await.unwrap();
+        assert_eq!(
+            addrs(&targets),
+            vec!["UDP 192.0.2.2:5060", "UDP 192.0.2.1:5060"]
+        );
+        // Served from the cache
+        assert_eq!(dns.queries(), queries);
+
+        clock.advance(Duration::from_secs(31));
+        let targets = resolver.resolve(uri).await.unwrap();
+        assert_eq!(
+            addrs(&targets),
+            vec!["UDP 192.0.2.1:5060", "UDP 192.0.2.2:5060"]
+        );
+
+        // Past the TTL the records are asked for again
+        clock.advance(Duration::from_secs(60));
+        resolver.resolve(uri).await.unwrap();
+        assert!(dns.queries() > queries);
+    }
+
+    /// Answers from the listed addresses; the rest are unreachable
+    struct Reachable {
+        answering: Vec<&'static str>,
+        attempts: Mutex<Vec<String>>,
+    }
+
+    #[async_trait]
+    impl InviteForwarder for Reachable {
+        async fn forward(
+            &self,
+            target: &str,
+            request: &SipRequest,
+        ) -> Result<SipResponse, SipError> {
+            self.attempts.lock().unwrap().push(target.to_string());
+            if self.answering.iter().any(|addr| target.contains(addr)) {
+                ResponseBuilder::new(200).build_for_request(request)
+            } else {
+                Err(SipError::TransportError(
+                    "ICMP port unreachable".to_string(),
+                ))
+            }
+        }
+    }
+
+    fn invite(uri: &str) -> SipRequest {
+        let text = format!(
+            "INVITE {uri} SIP/2.0\r\n\
+             Via: SIP/2.0/UDP 192.0.2.100:5060;branch=z9hG4bK1\r\n\
+             Max-Forwards: 70\r\n\
+             From: <sip:alice@pbx.example.com>;tag=a1\r\n\
+             To: <{uri}>\r\n\
+             Call-ID: failover-test\r\n\
+             CSeq: 1 INVITE\r\n\
+             Content-Length: 0\r\n\r\n"
+        );
+        SipRequest::parse(text.as_bytes()).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_failover_to_next_target() {
+        let dns = StaticDns::new()
+            .srv(
+                "_sip._udp.carrier.example",
+                &[
+                    (10, 0, 5060, "a.carrier.example"),
+                    (20, 0, 5060, "b.carrier.example"),
+                ],
+            )
+            .host("a.carrier.example", "192.0.2.1")
+            .host("b.carrier.example", "192.0.2.2");
+        let resolver = SipResolver::new(SipResolverConfig::default(), Arc::new(dns));
+        let uas = Reachable {
+            answering: vec!["192.0.2.2"],
+            attempts: Mutex::new(Vec::new()),
+        };
+        let uri = "sip:+14155551234@carrier.example;transport=udp;user=phone";
+
+        let failover = FailoverForwarder::new(&uas, &resolver);
+        let response = failover.forward(uri, &invite(uri)).await.unwrap();
+        assert_eq!(response.status_code(), 200);
+        assert_eq!(
+            *uas.attempts.lock().unwrap(),
+            vec![
+                "sip:+14155551234@192.0.2.1:5060;transport=udp;user=phone",
+                "sip:+14155551234@192.0.2.2:5060;transport=udp;user=phone",
+            ]
+        );
+        assert_eq!(
+            failover.served_by().unwrap().addr,
+            "192.0.2.2:5060".parse().unwrap()
+        );
+
+        // The dead target is skipped first on the next call
+        uas.attempts.lock().unwrap().clear();
+        let failover = FailoverForwarder::new(&uas, &resolver);
+        failover.forward(uri, &invite(uri)).await.unwrap();
+        assert_eq!(uas.attempts.lock().unwrap().len(), 1);
+    }
+}
@@ -0,0 +1,296 @@
+//! Screening choice of a screened call's callee
+//!
+//! Once the screened user answers, they hear the caller's name and the
+//! options; the digits they press (RFC 2833 or INFO, through the call's
+//! DTMF stream) settle the call in the router. A user who does not choose
+//! in time sends the call to voicemail.
+
+use super::call_router::{CallRouter, ScreeningOutcome};
+use crate::domain::call_announcer::{AnnouncementRequest, CallAnnouncer};
+use crate::domain::call_screening::ScreeningService;
+use crate::infrastructure::ivr::DtmfDispatcher;
+use std::sync::Arc;
+use tokio::sync::broadcast;
+use tracing::{info, warn};
+
+/// Plays the screening prompts and applies the callee's choice
+pub struct CallScreener {
+    router: Arc<CallRouter>,
+    screening: Arc<ScreeningService>,
+    dtmf: Arc<DtmfDispatcher>,
+    announcer: Option<Arc<CallAnnouncer>>,
+}
+
+impl CallScreener {
+    pub fn new(
+        router: Arc<CallRouter>,
+        screening: Arc<ScreeningService>,
+        dtmf: Arc<DtmfDispatcher>,
+    ) -> Self {
+        Self {
+            router,
+            screening,
+            dtmf,
+            announcer: None,
+        }
+    }
+
+    /// Play the prompts into calls (otherwise the choice is only awaited)
+    pub fn with_announcer(mut self, announcer: Arc<CallAnnouncer>) -> Self {
+        self.announcer = Some(announcer);
+        self
+    }
+
+    /// The screened callee of `call_id` answered: play them the caller's
+    /// name and options and wait for their choice
+    pub async fn callee_answered(&self, call_id: &str) -> Result<ScreeningOutcome, String> {
+        // Digits pressed while the prompt plays count too
+        let mut digits = self.dtmf.subscribe(call_id);
+        let prompt = self.router.screened_callee_answered(call_id).await?;
+        self.play(prompt);
+
+        let deadline = tokio::time::Instant::now() + self.screening.policy().choice_timeout();
+        let outcome = loop {
+            let digit = match tokio::time::timeout_at(deadline, digits.recv()).await {
+                Ok(Ok(event)) => event.digit.to_char(),
+                Ok(Err(broadcast::error::RecvError::Lagged(_))) => continue,
+                Ok(Err(broadcast::error::RecvError::Closed)) | Err(_) => {
+                    info!("No screening choice for call {}, sending to voicemail", call_id);
+                    break self.router.screening_timed_out(call_id).await?;
+                }
+            };
+            if let Some(outcome) = self.router.screening_choice(call_id, digit).await? {
+                break Some(outcome);
+            }
+        };
+        let outcome = outcome.ok_or_else(|| format!("Call {} was already settled", call_id))?;
+
+        if let ScreeningOutcome::Rejected { prompt } = &outcome {
+            self.play(prompt.clone());
+        }
+        Ok(outcome)
+    }
+
+    fn play(&self, prompt: AnnouncementRequest) {
+        if let Some(announcer) = &self.announcer {
+            let call_id = prompt.call_id.clone();
+            if let Err(e) = announcer.play_announcement(prompt) {
+                warn!("Failed to play screening prompt to call {}: {}", call_id, e);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::application::call::{spawn_cdr_writer, CallApplicationService};
+    use crate::domain::call_screening::{ScreeningPolicy, ScreeningSettings};
+    use crate::domain::call_history::{classify_leg, CallDisposition, HistoryDirection};
+    use crate::domain::cdr::{CallDetailRecord, CallStatus, MockCdrRepository, END_REASON_VOICEMAIL};
+    use crate::infrastructure::ivr::{DtmfDigit, DtmfEvent};
+    use crate::infrastructure::messaging::InProcessEventBus;
+    use crate::infrastructure::protocols::sip::{CallState, Registrar};
+    use std::sync::Mutex;
+    use std::time::Duration;
+
+    const CALLER: &str = "sip:+15559999@trunk.example.net";
+    const CEO: &str = "sip:ceo@example.com";
+
+    struct Harness {
+        router: Arc<CallRouter>,
+        dtmf: Arc<DtmfDispatcher>,
+        screener: Arc<CallScreener>,
+        cdr: Arc<Mutex<Option<CallDetailRecord>>>,
+    }
+
+    fn harness(policy: ScreeningPolicy) -> Harness {
+        let cdr = Arc::new(Mutex::new(None));
+        let mut repository = MockCdrRepository::new();
+        let created = cdr.clone();
+        repository.expect_create().returning(move |record| {
+            *created.lock().unwrap() = Some(record.clone());
+            Ok(())
+        });
+        let current = cdr.clone();
+        repository
+            .expect_get_by_id()
+            .returning(move |_| Ok(current.lock().unwrap().clone()));
+        let updated = cdr.clone();
+        repository.expect_update().returning(move |record| {
+            *updated.lock().unwrap() = Some(record.clone());
+            Ok(())
+        });
+        let repository: Arc<MockCdrRepository> = Arc::new(repository);
+
+        let bus = Arc::new(InProcessEventBus::default());
+        spawn_cdr_writer(bus.as_ref(), repository.clone());
+
+        let screening = Arc::new(ScreeningService::new(policy));
+        screening.set_settings(
+            CEO,
+            ScreeningSettings {
+                enabled: true,
+                exempt: vec!["assistant".to_string()],
+            },
+        );
+        let router = Arc::new(
+            CallRouter::new(Arc::new(Registrar::new()))
+                .with_cdr_repository(repository)
+                .with_call_events(Arc::new(CallApplicationService::new(bus)))
+                .with_call_screening(screening.clone()),
+        );
+        let dtmf = Arc::new(DtmfDispatcher::new());
+        let screener = Arc::new(CallScreener::new(router.clone(), screening, dtmf.clone()));
+        Harness {
+            router,
+            dtmf,
+            screener,
+            cdr,
+        }
+    }
+
+    /// Place a screened call up to the point where the CEO answers
+    async fn ring_screened_call(harness: &Harness) {
+        harness
+            .router
+            .create_call("call-1".to_string(), CALLER.to_string(), CEO.to_string())
+            .await
+            .unwrap();
+        let prompt = harness.router.screen_call("call-1").await.unwrap().unwrap();
+        assert_eq!(prompt.party.as_deref(), Some(CALLER));
+        assert!(harness.router.is_screening("call-1").await);
+
+        harness
+            .router
+            .screening_name_recorded("call-1", "screening/call-1.wav")
+            .await
+            .unwrap();
+        assert_eq!(
+            harness.router.get_call_state("call-1").await,
+            Some(CallState::Ringing)
+        );
+    }
+
+    /// Answer as the CEO and press `digit` once the choice is awaited
+    async fn choose(harness: &Harness, digit: char) -> ScreeningOutcome {
+        let screener = harness.screener.clone();
+        let choice = tokio::spawn(async move { screener.callee_answered("call-1").await });
+        let event = DtmfEvent::new(DtmfDigit::from_char(digit).unwrap(), Duration::from_millis(100));
+        while harness.dtmf.publish("call-1", event.clone()) == 0 {
+            tokio::time::sleep(Duration::from_millis(5)).await;
+        }
+        choice.await.unwrap().unwrap()
+    }
+
+    async fn cdr_status(harness: &Harness) -> CallStatus {
+        for _ in 0..100 {
+            let status = harness.cdr.lock().unwrap().as_ref().unwrap().status;
+            if status != CallStatus::Active {
+                return status;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        panic!("CDR of call-1 not ended");
+    }
+
+    #[tokio::test]
+    async fn test_accept_bridges_the_legs() {
+        let harness = harness(ScreeningPolicy::default());
+        ring_screened_call(&harness).await;
+
+        let outcome = choose(&harness, '1').await;
+        assert!(matches!(outcome, ScreeningOutcome::Accepted));
+        assert!(!harness.router.is_screening("call-1").await);
+        let call = harness.router.get_active_call("call-1").await.unwrap();
+        assert_eq!(call.state, "Established");
+        assert_eq!(call.callee_uri, CEO);
+
+        harness.router.terminate_call("call-1").await.unwrap();
+        assert_eq!(cdr_status(&harness).await, CallStatus::Completed);
+    }
+
+    #[tokio::test]
+    async fn test_voicemail_diverts_with_recorded_name() {
+        let harness = harness(ScreeningPolicy::default());
+        ring_screened_call(&harness).await;
+
+        let outcome = choose(&harness, '2').await;
+        match outcome {
+            ScreeningOutcome::Voicemail {
+                target,
+                name_recording,
+            } => {
+                assert_eq!(target, "sip:vm-ceo@localhost");
+                assert_eq!(name_recording.as_deref(), Some("screening/call-1.wav"));
+            }
+            other => panic!("unexpected outcome {:?}", other),
+        }
+        // The caller is now connected to voicemail, not the CEO
+        let call = harness.router.get_active_call("call-1").await.unwrap();
+        assert_eq!(call.callee_uri, "sip:vm-ceo@localhost");
+        assert_eq!(call.callee_contact, None);
+
+        harness.router.terminate_call("call-1").await.unwrap();
+        assert_eq!(cdr_status(&harness).await, CallStatus::NoAnswer);
+        let cdr = harness.cdr.lock().unwrap().clone().unwrap();
+        assert!(cdr.answer_time.is_none());
+        assert_eq!(cdr.end_reason.as_deref(), Some(END_REASON_VOICEMAIL));
+        // The CEO's call history shows the call went to voicemail
+        assert_eq!(
+            classify_leg(&cdr, "ceo"),
+            Some((HistoryDirection::Incoming, CallDisposition::Voicemail))
+        );
+    }
+
+    #[tokio::test]
+    async fn test_reject_plays_declined_prompt_to_caller() {
+        let harness = harness(ScreeningPolicy::default());
+        ring_screened_call(&harness).await;
+
+        let outcome = choose(&harness, '3').await;
+        match outcome {
+            ScreeningOutcome::Rejected { prompt } => {
+                assert_eq!(prompt.audio_files, vec!["screening_declined"]);
+                assert_eq!(prompt.party.as_deref(), Some(CALLER));
+            }
+            other => panic!("unexpected outcome {:?}", other),
+        }
+        assert_eq!(
+            harness.router.get_call_state("call-1").await,
+            Some(CallState::Failed)
+        );
+        assert_eq!(cdr_status(&harness).await, CallStatus::Rejected);
+    }
+
+    #[tokio::test]
+    async fn test_no_choice_goes_to_voicemail() {
+        let harness = harness(ScreeningPolicy {
+            choice_timeout_secs: 0,
+            ..ScreeningPolicy::default()
+        });
+        ring_screened_call(&harness).await;
+
+        let outcome = harness.screener.callee_answered("call-1").await.unwrap();
+        assert!(matches!(outcome, ScreeningOutcome::Voicemail { .. }));
+        harness.router.terminate_call("call-1").await.unwrap();
+        assert_eq!(cdr_status(&harness).await, CallStatus::NoAnswer);
+    }
+
+    #[tokio::test]
+    async fn test_exempt_caller_is_not_screened() {
+        let harness = harness(ScreeningPolicy::default());
+        harness
+            .router
+            .create_call(
+                "call-1".to_string(),
+                "sip:assistant@example.com".to_string(),
+                CEO.to_string(),
+            )
+            .await
+            .unwrap();
+
+        assert!(harness.router.screen_call("call-1").await.unwrap().is_none());
+        assert!(!harness.router.is_screening("call-1").await);
+    }
+}
@@ -1,7 +1,10 @@
 //! Simple SDP (Session Description Protocol) handling
 
-use std::net::IpAddr;
+use std::net::{IpAddr, SocketAddr};
+use crate::infrastructure::media::codec::{CodecNegotiator, PayloadMap};
+use crate::infrastructure::media::rtp::ExtMap;
 use crate::infrastructure::media::srtp::{SrtpMasterKey, SrtpProfile};
+use crate::infrastructure::media::StreamDirection;
 
 /// Simple SDP session
 #[derive(Debug, Clone)]
@@ -9,7 +12,13 @@ pub struct SdpSession {
     pub version: u32,
     pub origin: SdpOrigin,
     pub session_name: String,
+    /// Session-level connection (taken from the first m-line when the
+    /// offer only has media-level c= lines)
     pub connection: SdpConnection,
+    /// Session-level attributes other than direction, passed through as-is
+    /// (e.g. `group:BUNDLE 0 1`)
+    pub attributes: Vec<String>,
+    /// All m-lines, in order
     pub media: Vec<SdpMedia>,
 }
 
@@ -23,13 +32,30 @@ pub struct SdpOrigin {
     pub address: String,
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq)]
 pub struct SdpConnection {
     pub network_type: String,
     pub address_type: String,
     pub address: String,
 }
 
+impl SdpConnection {
+    /// Connection line for a local address (IP4 or IP6)
+    pub fn for_ip(ip: IpAddr) -> Self {
+        Self {
+            network_type: "IN".to_string(),
+            address_type: if ip.is_ipv4() { "IP4" } else { "IP6" }.to_string(),
+            address: ip.to_string(),
+        }
+    }
+
+    /// Connection address, without a multicast TTL/count suffix
+    pub fn ip(&self) -> Option<IpAddr> {
+        let address = self.address.split('/').next().unwrap_or_default();
+        address.parse().ok()
+    }
+}
+
 /// SRTP crypto line (SDES)
 #[derive(Debug, Clone)]
 pub struct SdpCrypto {
@@ -145,6 +171,228 @@ pub struct SdpMedia {
     pub formats: Vec<String>, // Codec payload types
     pub rtpmap: Vec<(String, String)>, // (payload_type, encoding)
     pub crypto: Vec<SdpCrypto>, // SRTP crypto lines
+    pub direction: StreamDirection,
+    /// Media-level connection overriding the session-level one
+    pub connection: Option<SdpConnection>,
+    /// Other attributes (fmtp, rtcp, ...), passed through in order
+    pub attributes: Vec<String>,
+}
+
+impl SdpMedia {
+    /// Whether the stream is rejected/disabled (port 0)
+    pub fn is_rejected(&self) -> bool {
+        self.port == 0
+    }
+
+    /// Zero-ported copy of this m-line, used to decline it in an answer
+    ///
+    /// RFC 3264 section 6 requires the answer to keep the m-line with at
+    /// least one of the offered formats.
+    pub fn rejected(&self) -> Self {
+        Self {
+            media_type: self.media_type.clone(),
+            port: 0,
+            protocol: self.protocol.clone(),
+            formats: self.formats.iter().take(1).cloned().collect(),
+            rtpmap: Vec::new(),
+            crypto: Vec::new(),
+            direction: self.direction,
+            connection: None,
+            attributes: Vec::new(),
+        }
+    }
+
+    /// Packet time from a=ptime, in milliseconds
+    pub fn ptime(&self) -> Option<u32> {
+        self.attribute_value("ptime")
+    }
+
+    /// Maximum packet time from a=maxptime, in milliseconds
+    pub fn max_ptime(&self) -> Option<u32> {
+        self.attribute_value("maxptime")
+    }
+
+    /// Set a=ptime, replacing any present
+    pub fn set_ptime(&mut self, ptime_ms: u32) {
+        self.attributes.retain(|a| !a.starts_with("ptime:"));
+        self.attributes.push(format!("ptime:{}", ptime_ms));
+    }
+
+    /// List exactly the negotiated codecs, numbered as negotiated, dropping
+    /// the fmtp lines of any others
+    pub fn set_payloads(&mut self, payloads: &PayloadMap) {
+        self.formats = payloads
+            .payload_types()
+            .iter()
+            .map(|pt| pt.to_string())
+            .collect();
+        let formats = &self.formats;
+        self.attributes
+            .retain(|a| fmtp_payload_type(a).is_none_or(|pt| formats.iter().any(|f| f == pt)));
+        self.rtpmap = payloads
+            .codecs
+            .iter()
+            .map(|codec| {
+                let encoding = match codec.channels {
+                    0 | 1 => codec.encoding(),
+                    channels => format!("{}/{}", codec.encoding(), channels),
+                };
+                (codec.payload_type.to_string(), encoding)
+            })
+            .chain(
+                payloads
+                    .telephone_event
+                    .map(|pt| (pt.to_string(), "telephone-event/8000".to_string())),
+            )
+            .collect();
+    }
+
+    /// Whether RTCP is multiplexed on the RTP port (a=rtcp-mux, RFC 5761)
+    pub fn rtcp_mux(&self) -> bool {
+        self.attributes.iter().any(|a| a == "rtcp-mux")
+    }
+
+    /// RTCP port and address from a=rtcp (RFC 3605), when not RTP+1
+    pub fn rtcp(&self) -> Option<(u16, Option<IpAddr>)> {
+        self.attributes.iter().find_map(|a| {
+            let mut parts = a.strip_prefix("rtcp:")?.split_whitespace();
+            let port = parts.next()?.parse().ok()?;
+            Some((port, parts.nth(2).and_then(|ip| ip.parse().ok())))
+        })
+    }
+
+    /// Advertise RTCP on `rtcp_port` (a=rtcp) and/or multiplexed on the RTP
+    /// port (a=rtcp-mux), replacing any present
+    pub fn set_rtcp(&mut self, rtcp_port: Option<u16>, rtcp_mux: bool) {
+        self.attributes
+            .retain(|a| a != "rtcp-mux" && !a.starts_with("rtcp:"));
+        if let Some(port) = rtcp_port {
+            self.attributes.push(format!("rtcp:{}", port));
+        }
+        if rtcp_mux {
+            self.attributes.push("rtcp-mux".to_string());
+        }
+    }
+
+    /// Whether receiver estimated maximum bitrate feedback is offered for
+    /// any format (a=rtcp-fb:<pt> goog-remb)
+    pub fn remb(&self) -> bool {
+        self.attributes.iter().any(|a| {
+            a.strip_prefix("rtcp-fb:")
+                .and_then(|fb| fb.split_whitespace().nth(1))
+                .is_some_and(|fb| fb == "goog-remb")
+        })
+    }
+
+    /// Advertise REMB feedback for `payload_type`, replacing any present
+    pub fn set_remb(&mut self, payload_type: u8) {
+        self.attributes
+            .retain(|a| !(a.starts_with("rtcp-fb:") && a.ends_with(" goog-remb")));
+        self.attributes.push(format!("rtcp-fb:{} goog-remb", payload_type));
+    }
+
+    /// RTP header extensions (a=extmap) of the stream
+    pub fn extmaps(&self) -> Vec<ExtMap> {
+        self.attributes
+            .iter()
+            .filter(|a| a.starts_with("extmap:"))
+            .filter_map(|a| ExtMap::parse(a))
+            .collect()
+    }
+
+    /// Local ID of the header extension `uri`, if listed
+    pub fn extmap_id(&self, uri: &str) -> Option<u8> {
+        self.extmaps().into_iter().find(|e| e.uri == uri).map(|e| e.id)
+    }
+
+    /// Encoding of a payload type from its rtpmap line
+    pub fn encoding_of(&self, payload_type: &str) -> Option<&str> {
+        self.rtpmap
+            .iter()
+            .find(|(pt, _)| pt == payload_type)
+            .map(|(_, encoding)| encoding.as_str())
+    }
+
+    /// Narrow this (local) m-line to the formats it shares with `offered`
+    ///
+    /// Formats keep the offer's order and payload type numbers, and the
+    /// offer's rtpmap and fmtp lines for them are copied verbatim: some
+    /// clients hard-fail on an answer that renumbers their codecs. Header
+    /// extensions we list are kept only when offered, with the offer's IDs.
+    /// Left unchanged when nothing is in common.
+    fn echo_offer(&mut self, offered: &SdpMedia) {
+        let accepted: Vec<String> = offered
+            .formats
+            .iter()
+            .filter(|pt| self.accepts(offered, pt))
+            .cloned()
+            .collect();
+        if accepted.is_empty() {
+            return;
+        }
+
+        self.rtpmap = accepted
+            .iter()
+            .filter_map(|pt| {
+                let encoding = offered.encoding_of(pt).or_else(|| self.encoding_of(pt))?;
+                Some((pt.clone(), encoding.to_string()))
+            })
+            .collect();
+        let fmtp = offered
+            .attributes
+            .iter()
+            .filter(|a| fmtp_payload_type(a).is_some_and(|pt| accepted.iter().any(|f| f == pt)));
+        let local_extmaps = self.extmaps();
+        let extmaps = offered
+            .extmaps()
+            .into_iter()
+            .filter(|offered| local_extmaps.iter().any(|local| local.uri == offered.uri))
+            .map(|extmap| extmap.to_attribute());
+        let others = self
+            .attributes
+            .iter()
+            .filter(|a| fmtp_payload_type(a).is_none() && !a.starts_with("extmap:"));
+        self.attributes = fmtp.chain(others).cloned().chain(extmaps).collect();
+        self.formats = accepted;
+    }
+
+    /// Whether an offered format is one of ours: dynamic payload types by
+    /// encoding, static ones without an rtpmap line by number
+    fn accepts(&self, offered: &SdpMedia, payload_type: &str) -> bool {
+        match offered.encoding_of(payload_type) {
+            Some(encoding) => self
+                .rtpmap
+                .iter()
+                .any(|(_, local)| same_encoding(local, encoding)),
+            None => {
+                payload_type.parse::<u8>().is_ok_and(|pt| pt < 96)
+                    && self.formats.iter().any(|f| f == payload_type)
+            }
+        }
+    }
+
+    fn attribute_value(&self, name: &str) -> Option<u32> {
+        self.attributes.iter().find_map(|a| {
+            a.strip_prefix(name)?
+                .strip_prefix(':')?
+                .trim()
+                .parse()
+                .ok()
+        })
+    }
+}
+
+/// Payload type an a=fmtp attribute applies to
+fn fmtp_payload_type(attribute: &str) -> Option<&str> {
+    attribute.strip_prefix("fmtp:")?.split_whitespace().next()
+}
+
+/// Whether two rtpmap encodings name the same codec and clock rate
+fn same_encoding(a: &str, b: &str) -> bool {
+    let mut a = a.split('/');
+    let mut b = b.split('/');
+    a.next().zip(b.next()).is_some_and(|(a, b)| a.eq_ignore_ascii_case(b))
+        && a.next().map(str::trim) == b.next().map(str::trim)
 }
 
 impl SdpSession {
@@ -161,11 +409,8 @@ impl SdpSession {
                 address: local_ip.to_string(),
             },
             session_name: "YakYak Call".to_string(),
-            connection: SdpConnection {
-                network_type: "IN".to_string(),
-                address_type: if local_ip.is_ipv4() { "IP4" } else { "IP6" }.to_string(),
-                address: local_ip.to_string(),
-            },
+            connection: SdpConnection::for_ip(local_ip),
+            attributes: Vec::new(),
             media: vec![SdpMedia {
                 media_type: "audio".to_string(),
                 port: local_port,
@@ -177,10 +422,41 @@ impl SdpSession {
                     ("101".to_string(), "telephone-event/8000".to_string()),
                 ],
                 crypto: Vec::new(),
+                direction: StreamDirection::SendRecv,
+                connection: None,
+                attributes: Vec::new(),
             }],
         }
     }
 
+    /// Answer to `offer` built from this (local, audio) session
+    ///
+    /// The answer has exactly the offer's m-lines in the offer's order: the
+    /// first offered audio stream is answered with our audio media, limited
+    /// to the offered codecs and numbered as offered, every other stream
+    /// (video, a second audio, ...) is declined with port 0.
+    pub fn answer_to(&self, offer: &SdpSession) -> SdpSession {
+        let mut local_audio = self.audio_media().cloned();
+        let media = offer
+            .media
+            .iter()
+            .map(|offered| {
+                if offered.media_type == "audio" && !offered.is_rejected() {
+                    if let Some(mut audio) = local_audio.take() {
+                        audio.echo_offer(offered);
+                        return audio;
+                    }
+                }
+                offered.rejected()
+            })
+            .collect();
+
+        SdpSession {
+            media,
+            ..self.clone()
+        }
+    }
+
     /// Convert to SDP string
     pub fn to_string(&self) -> String {
         let mut sdp = String::new();
@@ -211,6 +487,10 @@ impl SdpSession {
         // Time
         sdp.push_str("t=0 0\r\n");
 
+        for attribute in &self.attributes {
+            sdp.push_str(&format!("a={}\r\n", attribute));
+        }
+
         // Media descriptions
         for media in &self.media {
             sdp.push_str(&format!(
@@ -221,6 +501,20 @@ impl SdpSession {
                 media.formats.join(" ")
             ));
 
+            // Nothing but the m= line for a declined stream
+            if media.is_rejected() {
+                continue;
+            }
+
+            if let Some(conn) = &media.connection {
+                if *conn != self.connection {
+                    sdp.push_str(&format!(
+                        "c={} {} {}\r\n",
+                        conn.network_type, conn.address_type, conn.address
+                    ));
+                }
+            }
+
             // Crypto (SRTP)
             for crypto in &media.crypto {
                 sdp.push_str(&format!("a=crypto:{}\r\n", crypto.to_string()));
@@ -231,8 +525,12 @@ impl SdpSession {
                 sdp.push_str(&format!("a=rtpmap:{} {}\r\n", pt, encoding));
             }
 
-            // Send/receive
-            sdp.push_str("a=sendrecv\r\n");
+            for attribute in &media.attributes {
+                sdp.push_str(&format!("a={}\r\n", attribute));
+            }
+
+            // Direction
+            sdp.push_str(&format!("a={}\r\n", media.direction.as_str()));
         }
 
         sdp
@@ -244,8 +542,11 @@ impl SdpSession {
         let mut origin: Option<SdpOrigin> = None;
         let mut session_name = String::new();
         let mut connection: Option<SdpConnection> = None;
+        let mut attributes: Vec<String> = Vec::new();
         let mut media: Vec<SdpMedia> = Vec::new();
         let mut current_media: Option<SdpMedia> = None;
+        // Session-level direction applies to media without their own attribute
+        let mut session_direction = StreamDirection::SendRecv;
 
         for line in sdp_body.lines() {
             let line = line.trim();
@@ -292,11 +593,10 @@ impl SdpSession {
                             address: parts[2].to_string(),
                         };
 
-                        if current_media.is_none() {
-                            // Session-level connection
-                            connection = Some(conn);
+                        match current_media.as_mut() {
+                            Some(media) => media.connection = Some(conn),
+                            None => connection = Some(conn),
                         }
-                        // Media-level connection is currently ignored
                     }
                 }
                 "m=" => {
@@ -319,12 +619,20 @@ impl SdpSession {
                             formats,
                             rtpmap: Vec::new(),
                             crypto: Vec::new(),
+                            direction: session_direction,
+                            connection: None,
+                            attributes: Vec::new(),
                         });
                     }
                 }
                 "a=" => {
                     // Parse attributes
-                    if let Some(media) = current_media.as_mut() {
+                    if let Some(direction) = StreamDirection::from_attr(value) {
+                        match current_media.as_mut() {
+                            Some(media) => media.direction = direction,
+                            None => session_direction = direction,
+                        }
+                    } else if let Some(media) = current_media.as_mut() {
                         if value.starts_with("rtpmap:") {
                             let rtpmap_value = &value[7..]; // Skip "rtpmap:"
                             if let Some(space_pos) = rtpmap_value.find(' ') {
@@ -337,7 +645,11 @@ impl SdpSession {
                             if let Some(crypto) = SdpCrypto::parse(crypto_value) {
                                 media.crypto.push(crypto);
                             }
+                        } else {
+                            media.attributes.push(value.to_string());
                         }
+                    } else {
+                        attributes.push(value.to_string());
                     }
                 }
                 _ => {
@@ -351,19 +663,65 @@ impl SdpSession {
             media.push(m);
         }
 
-        // Validate required fields
+        // Validate required fields; without a session-level c= line every
+        // m-line carries its own (RFC 4566 5.7)
         let origin = origin?;
-        let connection = connection?;
+        let connection = connection.or_else(|| media.iter().find_map(|m| m.connection.clone()))?;
 
         Some(Self {
             version,
             origin,
             session_name,
             connection,
+            attributes,
             media,
         })
     }
 
+    /// Address media for the audio stream should be sent to
+    ///
+    /// A media-level c= line takes precedence over the session-level one.
+    pub fn audio_address(&self) -> Option<IpAddr> {
+        self.audio_media()
+            .and_then(|m| m.connection.as_ref())
+            .unwrap_or(&self.connection)
+            .ip()
+    }
+
+    /// RTP address of the audio stream, unless it is rejected
+    pub fn audio_rtp_address(&self) -> Option<SocketAddr> {
+        let port = self.audio_media().map(|m| m.port).filter(|port| *port != 0)?;
+        Some(SocketAddr::new(self.audio_address()?, port))
+    }
+
+    /// RTCP address of the audio stream: its RTP address when multiplexed,
+    /// else the a=rtcp port (and address), or RTP+1 without one
+    pub fn audio_rtcp_address(&self) -> Option<SocketAddr> {
+        let rtp = self.audio_rtp_address()?;
+        let audio = self.audio_media()?;
+        if audio.rtcp_mux() {
+            return Some(rtp);
+        }
+        Some(match audio.rtcp() {
+            Some((port, ip)) => SocketAddr::new(ip.unwrap_or(rtp.ip()), port),
+            None => SocketAddr::new(rtp.ip(), rtp.port().wrapping_add(1)),
+        })
+    }
+
+    /// Direction of the audio stream (sendrecv if there is none)
+    pub fn audio_direction(&self) -> StreamDirection {
+        self.audio_media()
+            .map(|m| m.direction)
+            .unwrap_or(StreamDirection::SendRecv)
+    }
+
+    /// Set the direction of every media stream
+    pub fn set_direction(&mut self, direction: StreamDirection) {
+        for media in self.media.iter_mut() {
+            media.direction = direction;
+        }
+    }
+
     /// Get media description for audio
     pub fn audio_media(&self) -> Option<&SdpMedia> {
         self.media.iter().find(|m| m.media_type == "audio")
@@ -382,6 +740,19 @@ impl SdpSession {
         }
     }
 
+    /// Codecs of the offered audio stream we accept, numbered as offered
+    pub fn negotiate_audio(&self, negotiator: &CodecNegotiator) -> PayloadMap {
+        let Some(audio) = self.audio_media() else {
+            return PayloadMap::default();
+        };
+        let rtpmap: Vec<(u8, String)> = audio
+            .rtpmap
+            .iter()
+            .filter_map(|(pt, encoding)| Some((pt.parse().ok()?, encoding.clone())))
+            .collect();
+        negotiator.negotiate_offer(&self.audio_codecs(), &rtpmap)
+    }
+
     /// Add SRTP crypto to audio media
     pub fn add_srtp_crypto(&mut self, master_key: &SrtpMasterKey, profile: SrtpProfile) {
         if let Some(media) = self.media.iter_mut().find(|m| m.media_type == "audio") {
@@ -394,6 +765,22 @@ impl SdpSession {
         }
     }
 
+    /// Replace the audio crypto lines with a new master key under the next
+    /// tag, returning that tag. None without SRTP audio.
+    pub fn rekey_srtp_crypto(
+        &mut self,
+        master_key: &SrtpMasterKey,
+        profile: SrtpProfile,
+    ) -> Option<u32> {
+        let media = self
+            .media
+            .iter_mut()
+            .find(|m| m.media_type == "audio" && !m.crypto.is_empty())?;
+        let tag = media.crypto.iter().map(|c| c.tag).max().unwrap_or(0) + 1;
+        media.crypto = vec![SdpCrypto::from_master_key(tag, master_key, profile)];
+        Some(tag)
+    }
+
     /// Get SRTP crypto from audio media
     pub fn get_srtp_crypto(&self) -> Option<(SrtpMasterKey, SrtpProfile)> {
         if let Some(audio) = self.audio_media() {
@@ -414,9 +801,75 @@ impl SdpSession {
     }
 }
 
+/// Per-dialog SDP origin
+///
+/// RFC 3264 section 8 requires the o= line to keep the same session id for
+/// the whole dialog and to increment the version only when the description
+/// changes. Answering a retransmitted or unchanged offer must therefore
+/// produce the same o= line as before.
+#[derive(Debug, Clone)]
+pub struct SdpOriginState {
+    session_id: String,
+    version: u64,
+    last_rendered: Option<String>,
+}
+
+impl SdpOriginState {
+    /// Create a new origin with a fresh session id
+    pub fn new() -> Self {
+        Self {
+            session_id: rand::random::<u32>().to_string(),
+            version: 1,
+            last_rendered: None,
+        }
+    }
+
+    /// Session id used on every o= line of this dialog
+    pub fn session_id(&self) -> &str {
+        &self.session_id
+    }
+
+    /// Current session version
+    pub fn version(&self) -> u64 {
+        self.version
+    }
+
+    /// Stamp the dialog origin onto `session` and render it
+    ///
+    /// The version is incremented only if the rendered description differs
+    /// from the previous one.
+    pub fn render(&mut self, session: &mut SdpSession) -> String {
+        session.origin.session_id = self.session_id.clone();
+        session.origin.session_version = self.version.to_string();
+        let body = session.to_string();
+
+        match &self.last_rendered {
+            Some(last) if *last == body => body,
+            None => {
+                self.last_rendered = Some(body.clone());
+                body
+            }
+            Some(_) => {
+                self.version += 1;
+                session.origin.session_version = self.version.to_string();
+                let body = session.to_string();
+                self.last_rendered = Some(body.clone());
+                body
+            }
+        }
+    }
+}
+
+impl Default for SdpOriginState {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::infrastructure::media::rtp::AUDIO_LEVEL_URI;
 
     #[test]
     fn test_create_sdp() {
@@ -473,6 +926,39 @@ a=rtpmap:8 PCMA/8000
         assert_eq!(audio.media_type, "audio");
     }
 
+    #[test]
+    fn test_parse_direction() {
+        let sdp_str = "v=0\r\no=- 1 1 IN IP4 10.0.0.1\r\ns=-\r\nc=IN IP4 10.0.0.1\r\nt=0 0\r\na=recvonly\r\nm=audio 4000 RTP/AVP 0\r\nm=video 4002 RTP/AVP 96\r\na=inactive\r\n";
+        let sdp = SdpSession::parse(sdp_str).unwrap();
+
+        assert_eq!(sdp.media[0].direction, StreamDirection::RecvOnly);
+        assert_eq!(sdp.media[1].direction, StreamDirection::Inactive);
+        assert_eq!(sdp.audio_direction(), StreamDirection::RecvOnly);
+    }
+
+    #[test]
+    fn test_origin_version_only_changes_with_content() {
+        let local_ip: IpAddr = "10.0.0.5".parse().unwrap();
+        let mut origin = SdpOriginState::new();
+
+        let mut first = SdpSession::create_audio_session(local_ip, 20000);
+        let body1 = origin.render(&mut first);
+
+        // Same content rendered again: byte-identical, same version
+        let mut again = SdpSession::create_audio_session(local_ip, 20000);
+        assert_eq!(origin.render(&mut again), body1);
+        assert_eq!(origin.version(), 1);
+
+        // Direction change bumps the version but keeps the session id
+        let mut held = SdpSession::create_audio_session(local_ip, 20000);
+        held.set_direction(StreamDirection::SendOnly);
+        let body2 = origin.render(&mut held);
+        let parsed = SdpSession::parse(&body2).unwrap();
+        assert_eq!(parsed.origin.session_id, origin.session_id());
+        assert_eq!(parsed.origin.session_version, "2");
+        assert_eq!(parsed.audio_direction(), StreamDirection::SendOnly);
+    }
+
     #[test]
     fn test_sdp_crypto_parse() {
         let crypto_str = "1 AES_CM_128_HMAC_SHA1_80 inline:d0RmdmcmVCspeEc3QGZiNWpVLFJhQX1cfHAwJSoj";
@@ -535,6 +1021,26 @@ a=rtpmap:8 PCMA/8000
         assert_eq!(decoded_key.salt, master_key.salt);
     }
 
+    #[test]
+    fn test_rekey_srtp_crypto() {
+        use crate::infrastructure::media::srtp::{SrtpMasterKey, SrtpProfile};
+
+        let local_ip: IpAddr = "192.168.1.100".parse().unwrap();
+        let mut sdp = SdpSession::create_audio_session(local_ip, 10000);
+        let profile = SrtpProfile::Aes128CmHmacSha1_80;
+        assert_eq!(sdp.rekey_srtp_crypto(&SrtpMasterKey::generate(profile), profile), None);
+
+        sdp.add_srtp_crypto(&SrtpMasterKey::generate(profile), profile);
+        let new_key = SrtpMasterKey::generate(profile);
+        assert_eq!(sdp.rekey_srtp_crypto(&new_key, profile), Some(2));
+
+        let parsed = SdpSession::parse(&sdp.to_string()).unwrap();
+        assert_eq!(parsed.audio_media().unwrap().crypto.len(), 1);
+        assert_eq!(parsed.audio_media().unwrap().crypto[0].tag, 2);
+        let (decoded_key, _) = parsed.get_srtp_crypto().unwrap();
+        assert_eq!(decoded_key.key, new_key.key);
+    }
+
     #[test]
     fn test_parse_sdp_with_crypto() {
         let sdp_str = r#"v=0
@@ -559,4 +1065,344 @@ a=rtpmap:8 PCMA/8000
         assert_eq!(crypto.tag, 1);
         assert_eq!(crypto.crypto_suite, "AES_CM_128_HMAC_SHA1_80");
     }
+
+    /// Linphone desktop offering audio and video
+    const LINPHONE_OFFER: &str = "v=0\r\n\
+o=alice 1853 3520 IN IP4 192.168.1.20\r\n\
+s=Talk\r\n\
+c=IN IP4 192.168.1.20\r\n\
+t=0 0\r\n\
+a=rtcp-xr:rcvr-rtt=all:10000 stat-summary=loss,dup,jitt,TTL voip-metrics\r\n\
+m=audio 7078 RTP/AVP 96 0 8 101\r\n\
+a=rtpmap:96 opus/48000/2\r\n\
+a=fmtp:96 useinbandfec=1\r\n\
+a=rtpmap:101 telephone-event/8000\r\n\
+a=rtcp-fb:* trr-int 1000\r\n\
+m=video 9078 RTP/AVP 96 97\r\n\
+a=rtpmap:96 VP8/90000\r\n\
+a=rtpmap:97 H264/90000\r\n\
+a=fmtp:97 profile-level-id=42801F\r\n\
+a=rtcp-fb:* nack pli\r\n";
+
+    /// Chrome (WebRTC): no session-level c=, BUNDLE group, IPv6 audio
+    const CHROME_OFFER: &str = "v=0\r\n\
+o=- 4611731400430051336 2 IN IP4 127.0.0.1\r\n\
+s=-\r\n\
+t=0 0\r\n\
+a=group:BUNDLE 0 1\r\n\
+a=extmap-allow-mixed\r\n\
+a=msid-semantic: WMS\r\n\
+m=audio 49203 UDP/TLS/RTP/SAVPF 111 0 8 126\r\n\
+c=IN IP6 2001:db8::5\r\n\
+a=rtcp:9 IN IP4 0.0.0.0\r\n\
+a=mid:0\r\n\
+a=sendrecv\r\n\
+a=rtcp-mux\r\n\
+a=rtpmap:111 opus/48000/2\r\n\
+a=fmtp:111 minptime=10;useinbandfec=1\r\n\
+a=rtpmap:126 telephone-event/8000\r\n\
+m=video 49204 UDP/TLS/RTP/SAVPF 96 97\r\n\
+c=IN IP4 203.0.113.9\r\n\
+a=mid:1\r\n\
+a=recvonly\r\n\
+a=rtpmap:96 VP8/90000\r\n\
+a=rtpmap:97 rtx/90000\r\n\
+a=fmtp:97 apt=96\r\n";
+
+    /// Yealink video phone: video listed first, second audio disabled
+    const YEALINK_OFFER: &str = "v=0\r\n\
+o=- 20024 20024 IN IP4 10.1.2.30\r\n\
+s=SDP data\r\n\
+c=IN IP4 10.1.2.30\r\n\
+t=0 0\r\n\
+m=video 11802 RTP/AVP 99 100\r\n\
+b=TIAS:2048000\r\n\
+a=rtpmap:99 H264/90000\r\n\
+a=fmtp:99 profile-level-id=42801f;packetization-mode=1\r\n\
+a=rtpmap:100 H264/90000\r\n\
+m=audio 11800 RTP/AVP 9 0 8 101\r\n\
+a=rtpmap:9 G722/8000\r\n\
+a=ptime:20\r\n\
+a=rtpmap:101 telephone-event/8000\r\n\
+a=fmtp:101 0-15\r\n\
+m=audio 0 RTP/AVP 0\r\n";
+
+    /// Polycom VVX: telephone-event on 127
+    const POLYCOM_OFFER: &str = "v=0\r\n\
+o=- 1696241402 1696241402 IN IP4 10.1.2.41\r\n\
+s=Polycom IP Phone\r\n\
+c=IN IP4 10.1.2.41\r\n\
+t=0 0\r\n\
+a=sendrecv\r\n\
+m=audio 2222 RTP/AVP 9 102 0 8 18 127\r\n\
+a=rtpmap:9 G722/8000\r\n\
+a=rtpmap:102 G7221/16000\r\n\
+a=fmtp:102 bitrate=32000\r\n\
+a=rtpmap:0 PCMU/8000\r\n\
+a=rtpmap:8 PCMA/8000\r\n\
+a=rtpmap:18 G729/8000\r\n\
+a=fmtp:18 annexb=no\r\n\
+a=rtpmap:127 telephone-event/8000\r\n\
+a=fmtp:127 0-15\r\n";
+
+    /// Zoiper: opus renumbered to 106, telephone-event on 101
+    const ZOIPER_OFFER: &str = "v=0\r\n\
+o=Z 1700000000 1 IN IP4 192.168.1.52\r\n\
+s=Z\r\n\
+c=IN IP4 192.168.1.52\r\n\
+t=0 0\r\n\
+m=audio 52140 RTP/AVP 106 0 8 3 101\r\n\
+a=rtpmap:106 opus/48000/2\r\n\
+a=fmtp:106 sprop-maxcapturerate=24000\r\n\
+a=rtpmap:3 GSM/8000\r\n\
+a=rtpmap:101 telephone-event/8000\r\n\
+a=fmtp:101 0-16\r\n\
+a=sendrecv\r\n";
+
+    /// Mobile softphone: telephone-event on the first dynamic payload type
+    const MOBILE_OFFER: &str = "v=0\r\n\
+o=- 3911052871 3911052871 IN IP4 172.16.4.9\r\n\
+s=-\r\n\
+c=IN IP4 172.16.4.9\r\n\
+t=0 0\r\n\
+m=audio 4000 RTP/AVP 98 8 0 96\r\n\
+a=rtpmap:98 opus/48000/2\r\n\
+a=fmtp:98 useinbandfec=1;usedtx=1\r\n\
+a=rtpmap:8 PCMA/8000\r\n\
+a=rtpmap:0 PCMU/8000\r\n\
+a=rtpmap:96 telephone-event/8000\r\n\
+a=fmtp:96 0-16\r\n\
+a=ptime:20\r\n";
+
+    /// Answer negotiated the way the INVITE handler does it
+    fn negotiated_answer(offer: &str) -> (SdpSession, PayloadMap) {
+        let offer = SdpSession::parse(offer).unwrap();
+        let payloads = offer.negotiate_audio(&CodecNegotiator::new());
+        let local_ip: IpAddr = "192.168.1.10".parse().unwrap();
+        let mut local = SdpSession::create_audio_session(local_ip, 20000);
+        local.media[0].set_payloads(&payloads);
+        let answer = local.answer_to(&offer);
+        (SdpSession::parse(&answer.to_string()).unwrap(), payloads)
+    }
+
+    fn answer_for(offer: &str) -> SdpSession {
+        let offer = SdpSession::parse(offer).unwrap();
+        let local_ip: IpAddr = "192.168.1.10".parse().unwrap();
+        let answer = SdpSession::create_audio_session(local_ip, 20000).answer_to(&offer);
+        SdpSession::parse(&answer.to_string()).unwrap()
+    }
+
+    fn media_lines(sdp: &SdpSession) -> Vec<(String, u16)> {
+        sdp.media
+            .iter()
+            .map(|m| (m.media_type.clone(), m.port))
+            .collect()
+    }
+
+    #[test]
+    fn test_linphone_offer_rejects_video() {
+        let offer = SdpSession::parse(LINPHONE_OFFER).unwrap();
+        assert_eq!(offer.media.len(), 2);
+        assert_eq!(offer.audio_codecs(), vec![96, 0, 8, 101]);
+        assert_eq!(
+            offer.attributes,
+            vec!["rtcp-xr:rcvr-rtt=all:10000 stat-summary=loss,dup,jitt,TTL voip-metrics"]
+        );
+        assert_eq!(offer.media[0].attributes, vec!["fmtp:96 useinbandfec=1", "rtcp-fb:* trr-int 1000"]);
+
+        let answer = answer_for(LINPHONE_OFFER);
+        assert_eq!(
+            media_lines(&answer),
+            vec![("audio".to_string(), 20000), ("video".to_string(), 0)]
+        );
+        assert!(answer.media[1].rtpmap.is_empty());
+    }
+
+    #[test]
+    fn test_chrome_offer_media_level_connections() {
+        let offer = SdpSession::parse(CHROME_OFFER).unwrap();
+        assert_eq!(offer.attributes, vec!["group:BUNDLE 0 1", "extmap-allow-mixed", "msid-semantic: WMS"]);
+        assert_eq!(offer.media[1].direction, StreamDirection::RecvOnly);
+
+        // Session connection falls back to the first m-line's
+        assert_eq!(offer.connection.address_type, "IP6");
+        assert_eq!(offer.audio_address(), Some("2001:db8::5".parse().unwrap()));
+        assert_eq!(
+            offer.media[1].connection.as_ref().and_then(|c| c.ip()),
+            Some("203.0.113.9".parse().unwrap())
+        );
+
+        // Unknown attributes survive a round trip
+        let reparsed = SdpSession::parse(&offer.to_string()).unwrap();
+        assert_eq!(reparsed.attributes, offer.attributes);
+        assert_eq!(reparsed.media[0].attributes, offer.media[0].attributes);
+        assert_eq!(reparsed.media[1].connection, offer.media[1].connection);
+
+        let answer = answer_for(CHROME_OFFER);
+        assert_eq!(
+            media_lines(&answer),
+            vec![("audio".to_string(), 20000), ("video".to_string(), 0)]
+        );
+    }
+
+    #[test]
+    fn test_yealink_offer_keeps_mline_order() {
+        let offer = SdpSession::parse(YEALINK_OFFER).unwrap();
+        assert_eq!(offer.audio_media().unwrap().port, 11800);
+        assert_eq!(offer.audio_codecs(), vec![9, 0, 8, 101]);
+        assert_eq!(offer.audio_media().unwrap().ptime(), Some(20));
+        assert_eq!(offer.audio_media().unwrap().max_ptime(), None);
+
+        let answer = answer_for(YEALINK_OFFER);
+        assert_eq!(
+            media_lines(&answer),
+            vec![
+                ("video".to_string(), 0),
+                ("audio".to_string(), 20000),
+                ("audio".to_string(), 0),
+            ]
+        );
+        assert_eq!(answer.media[0].formats, vec!["99"]);
+        assert_eq!(answer.audio_media().unwrap().port, 20000);
+    }
+
+    #[test]
+    fn test_ipv6_session_connection() {
+        let local_ip: IpAddr = "2001:db8::10".parse().unwrap();
+        let sdp = SdpSession::create_audio_session(local_ip, 30000);
+        let sdp_str = sdp.to_string();
+        assert!(sdp_str.contains("c=IN IP6 2001:db8::10\r\n"));
+
+        let parsed = SdpSession::parse(&sdp_str).unwrap();
+        assert_eq!(parsed.audio_address(), Some(local_ip));
+    }
+
+    #[test]
+    fn test_answer_echoes_offered_payload_types() {
+        // telephone-event on 127, static codecs only
+        let (answer, payloads) = negotiated_answer(POLYCOM_OFFER);
+        let audio = answer.audio_media().unwrap();
+        assert_eq!(audio.formats, vec!["9", "0", "8", "127"]);
+        assert_eq!(audio.encoding_of("127"), Some("telephone-event/8000"));
+        assert_eq!(audio.attributes, vec!["fmtp:127 0-15"]);
+        assert_eq!(payloads.primary().unwrap().payload_type, 9);
+        assert!(payloads.is_telephone_event(127));
+
+        // telephone-event on 101, opus on 106
+        let (answer, payloads) = negotiated_answer(ZOIPER_OFFER);
+        let audio = answer.audio_media().unwrap();
+        assert_eq!(audio.formats, vec!["106", "0", "8", "101"]);
+        assert_eq!(audio.encoding_of("106"), Some("opus/48000/2"));
+        assert_eq!(
+            audio.attributes,
+            vec!["fmtp:106 sprop-maxcapturerate=24000", "fmtp:101 0-16"]
+        );
+        assert_eq!(payloads.primary().unwrap().name, "opus");
+        assert_eq!(payloads.primary().unwrap().payload_type, 106);
+        assert!(payloads.is_telephone_event(101));
+
+        // telephone-event on 96, opus on 98
+        let (answer, payloads) = negotiated_answer(MOBILE_OFFER);
+        let audio = answer.audio_media().unwrap();
+        assert_eq!(audio.formats, vec!["98", "8", "0", "96"]);
+        assert_eq!(
+            audio.rtpmap,
+            vec![
+                ("98".to_string(), "opus/48000/2".to_string()),
+                ("8".to_string(), "PCMA/8000".to_string()),
+                ("0".to_string(), "PCMU/8000".to_string()),
+                ("96".to_string(), "telephone-event/8000".to_string()),
+            ]
+        );
+        assert_eq!(
+            audio.attributes,
+            vec!["fmtp:98 useinbandfec=1;usedtx=1", "fmtp:96 0-16"]
+        );
+        assert!(payloads.is_telephone_event(96));
+        assert!(payloads.codec(111).is_none());
+    }
+
+    #[test]
+    fn test_static_answer_limited_to_offer() {
+        // Our default formats are 0 8 101: offered telephone-event on 96
+        // is answered as 96, and PCMU (not offered) is left out
+        let offer = MOBILE_OFFER.replace("RTP/AVP 98 8 0 96", "RTP/AVP 98 8 96");
+        let answer = answer_for(&offer);
+        let audio = answer.audio_media().unwrap();
+        assert_eq!(audio.formats, vec!["8", "96"]);
+        assert_eq!(audio.attributes, vec!["fmtp:96 0-16"]);
+    }
+
+    #[test]
+    fn test_answer_keeps_offered_audio_level_extension() {
+        let offer = SdpSession::parse(&MOBILE_OFFER.replace(
+            "a=ptime:20\r\n",
+            "a=ptime:20\r\na=extmap:5 urn:ietf:params:rtp-hdrext:ssrc-audio-level\r\n",
+        ))
+        .unwrap();
+        let local_ip: IpAddr = "192.168.1.10".parse().unwrap();
+        let mut local = SdpSession::create_audio_session(local_ip, 20000);
+        local.media[0]
+            .attributes
+            .push("extmap:1 urn:ietf:params:rtp-hdrext:ssrc-audio-level".to_string());
+
+        // Answered with the offer's ID
+        let answer = SdpSession::parse(&local.answer_to(&offer).to_string()).unwrap();
+        let audio = answer.audio_media().unwrap();
+        assert_eq!(audio.extmap_id(AUDIO_LEVEL_URI), Some(5));
+
+        // Left out when not offered
+        let offer = SdpSession::parse(MOBILE_OFFER).unwrap();
+        let answer = SdpSession::parse(&local.answer_to(&offer).to_string()).unwrap();
+        assert!(answer.audio_media().unwrap().extmaps().is_empty());
+    }
+
+    #[test]
+    fn test_rtcp_address_and_answer() {
+        // rtcp-mux takes precedence over Chrome's placeholder a=rtcp
+        let chrome = SdpSession::parse(CHROME_OFFER).unwrap();
+        assert!(chrome.audio_media().unwrap().rtcp_mux());
+        assert_eq!(chrome.audio_rtcp_address(), chrome.audio_rtp_address());
+
+        let linphone = SdpSession::parse(LINPHONE_OFFER).unwrap();
+        assert!(!linphone.audio_media().unwrap().rtcp_mux());
+        assert_eq!(
+            linphone.audio_rtcp_address(),
+            Some("192.168.1.20:7079".parse().unwrap())
+        );
+
+        let local_ip: IpAddr = "192.168.1.10".parse().unwrap();
+        let mut local = SdpSession::create_audio_session(local_ip, 20000);
+        local.media[0].set_rtcp(Some(20001), false);
+        let answer = SdpSession::parse(&local.answer_to(&linphone).to_string()).unwrap();
+        assert_eq!(answer.audio_media().unwrap().rtcp(), Some((20001, None)));
+        assert_eq!(answer.audio_rtcp_address(), Some("192.168.1.10:20001".parse().unwrap()));
+
+        local.media[0].set_rtcp(None, true);
+        let answer = SdpSession::parse(&local.answer_to(&chrome).to_string()).unwrap();
+        let audio = answer.audio_media().unwrap();
+        assert!(audio.rtcp_mux());
+        assert_eq!(audio.rtcp(), None);
+        assert!(!audio.remb());
+    }
+
+    #[test]
+    fn test_remb_feedback() {
+        let chrome = SdpSession::parse(CHROME_OFFER).unwrap();
+        assert!(!chrome.audio_media().unwrap().remb());
+        let offer = CHROME_OFFER.replace(
+            "a=rtpmap:111 opus/48000/2\r\n",
+            "a=rtpmap:111 opus/48000/2\r\na=rtcp-fb:111 goog-remb\r\n",
+        );
+        let offer = SdpSession::parse(&offer).unwrap();
+        assert!(offer.audio_media().unwrap().remb());
+
+        let mut local = SdpSession::create_audio_session("192.168.1.10".parse().unwrap(), 20000);
+        local.media[0].set_remb(111);
+        local.media[0].set_remb(111);
+        let answer = SdpSession::parse(&local.answer_to(&offer).to_string()).unwrap();
+        let audio = answer.audio_media().unwrap();
+        assert!(audio.remb());
+        assert_eq!(audio.attributes.iter().filter(|a| a.starts_with("rtcp-fb:")).count(), 1);
+    }
 }
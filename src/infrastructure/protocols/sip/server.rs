@@ -1,21 +1,48 @@
 //! SIP server implementation (Simplified version)
 
 use super::builder::ResponseBuilder;
-use super::handler::SipHandler;
-use super::message::{SipError, SipMessage, SipMethod};
-use super::transport::{IncomingMessage, TcpTransport, Transport, UdpTransport};
+use super::connection::{ConnectionConfig, ConnectionTable};
+use super::handler::{SimpleTransactionUser, SipHandler, TransactionAction, TransactionHandle, TransactionUser};
+use super::header_rules::HeaderRulesEngine;
+use super::hops::HopTracker;
+use super::listener::{
+    Listener, ListenerActivity, ListenerInfo, ListenerOutcome, ListenerReport, ListenerRoute,
+    ListenerSpec, ListenerState, ListenerTransport,
+};
+use super::message::{SipError, SipMessage, SipMethod, SipRequest, SipResponse};
+use super::overload::OverloadMonitor;
+use super::quirks::QuirksRegistry;
+use super::transaction::{extract_branch, SipTimerConfig, TimerType};
+use super::transport::{
+    learned_aliases, IncomingMessage, OutgoingMessage, TcpTransport, TlsTransport, Transport,
+    TransportProtocol, UdpTransport,
+};
+use crate::application::events::EventBus;
+use crate::domain::call::CallEvent;
+use crate::infrastructure::telemetry::CallSpans;
+use rsip::Header;
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
-use std::net::SocketAddr;
-use std::sync::Arc;
-use tokio::sync::{mpsc, RwLock};
-use tracing::{debug, error, info, warn};
+use std::net::{IpAddr, SocketAddr};
+use std::sync::{Arc, Mutex as StdMutex, OnceLock, RwLock as StdRwLock};
+use std::time::Duration;
+use tokio::net::UdpSocket;
+use tokio::sync::{broadcast, mpsc, RwLock};
+use tokio::task::JoinHandle;
+use tracing::{debug, error, info, info_span, warn, Instrument, Span};
 
 /// SIP server configuration
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct SipServerConfig {
     pub udp_bind: SocketAddr,
     pub tcp_bind: SocketAddr,
+    /// Additional IPv6 UDP listener (`udp_bind` may itself be `[::]` for
+    /// a dual-stack socket instead)
+    #[serde(default)]
+    pub udp6_bind: Option<SocketAddr>,
+    /// Additional IPv6 TCP listener
+    #[serde(default)]
+    pub tcp6_bind: Option<SocketAddr>,
     pub domain: String,
     pub enable_tcp: bool,
     /// Enable TLS transport (SIPS)
@@ -26,6 +53,20 @@ pub struct SipServerConfig {
     pub tls_cert_path: String,
     /// Path to TLS private key file
     pub tls_key_path: String,
+    /// Close TCP connections idle for this many seconds
+    pub tcp_idle_timeout_secs: u64,
+    /// Maximum number of open TCP connections
+    pub tcp_max_connections: usize,
+    /// Seconds a removed listener keeps serving its dialogs before closing
+    #[serde(default = "default_listener_drain_timeout_secs")]
+    pub listener_drain_timeout_secs: u64,
+    /// Transaction timers (`[sip.timers]`)
+    #[serde(default)]
+    pub timers: SipTimerConfig,
+}
+
+fn default_listener_drain_timeout_secs() -> u64 {
+    300
 }
 
 impl Default for SipServerConfig {
@@ -33,201 +74,834 @@ impl Default for SipServerConfig {
         Self {
             udp_bind: "0.0.0.0:5060".parse().unwrap(),
             tcp_bind: "0.0.0.0:5060".parse().unwrap(),
+            udp6_bind: None,
+            tcp6_bind: None,
             domain: "localhost".to_string(),
             enable_tcp: true,
             enable_tls: false,
             tls_bind: "0.0.0.0:5061".parse().unwrap(),
             tls_cert_path: "certs/server.crt".to_string(),
             tls_key_path: "certs/server.key".to_string(),
+            tcp_idle_timeout_secs: 600,
+            tcp_max_connections: 1024,
+            listener_drain_timeout_secs: default_listener_drain_timeout_secs(),
+            timers: SipTimerConfig::default(),
+        }
+    }
+}
+
+/// Sockets and connections server-originated requests can leave on
+struct OutboundRoute {
+    route: ListenerRoute,
+    local_addr: SocketAddr,
+    draining: bool,
+    /// The request's dialog was established over this listener
+    has_dialog: bool,
+}
+
+/// Destination as the socket's family expects it: a dual-stack (`[::]`)
+/// socket reaches IPv4 peers through their v4-mapped address
+fn udp_destination(socket: &UdpSocket, destination: SocketAddr) -> SocketAddr {
+    match (socket.local_addr(), destination.ip()) {
+        (Ok(local), IpAddr::V4(v4)) if local.is_ipv6() => {
+            SocketAddr::new(IpAddr::V6(v4.to_ipv6_mapped()), destination.port())
+        }
+        _ => destination,
+    }
+}
+
+/// How often a draining listener is checked for remaining dialogs
+const DRAIN_CHECK_INTERVAL: Duration = Duration::from_millis(250);
+
+type Listeners = Arc<StdRwLock<Vec<Listener>>>;
+
+/// Via branch, Call-ID and method of a server transaction (a CANCEL
+/// shares its INVITE's branch; the Call-ID keeps apart clients that reuse
+/// branches)
+type TransactionKey = (String, String, SipMethod);
+
+/// Transaction users by method, and the server transactions they answer
+#[derive(Default)]
+struct Dispatcher {
+    handlers: RwLock<HashMap<SipMethod, Arc<dyn TransactionUser>>>,
+    /// Latest response of transactions in progress or recently completed
+    transactions: StdMutex<HashMap<TransactionKey, Option<SipResponse>>>,
+    /// Refuses new dialogs while the server is overloaded
+    overload: OnceLock<Arc<OverloadMonitor>>,
+    /// Timers of the transactions, by the transport their request came on
+    timers: SipTimerConfig,
+}
+
+impl Dispatcher {
+    async fn handler(&self, method: SipMethod) -> Option<Arc<dyn TransactionUser>> {
+        self.handlers.read().await.get(&method).cloned()
+    }
+
+    /// Start the transaction of a new request, or return the latest
+    /// response of the transaction `key` retransmits (`Some(None)` while
+    /// it has none yet)
+    fn begin(&self, key: &TransactionKey) -> Option<Option<SipResponse>> {
+        let mut transactions = self.transactions.lock().unwrap();
+        match transactions.get(key) {
+            Some(latest) => Some(latest.clone()),
+            None => {
+                transactions.insert(key.clone(), None);
+                None
+            }
+        }
+    }
+
+    fn record(&self, key: &TransactionKey, response: &SipResponse) {
+        if let Some(latest) = self.transactions.lock().unwrap().get_mut(key) {
+            *latest = Some(response.clone());
+        }
+    }
+
+    /// How long a completed transaction answers retransmissions of its
+    /// request with its final response: Timer H for INVITE (waiting for the
+    /// ACK), Timer J otherwise, from the T1 of `transport`
+    fn linger(&self, method: SipMethod, transport: TransportProtocol) -> Duration {
+        let timers = self.timers.timers_for(transport, None);
+        let timer = match method {
+            SipMethod::Invite => TimerType::TimerH,
+            _ => TimerType::TimerJ,
+        };
+        timer.default_duration(&timers, transport != TransportProtocol::Udp)
+    }
+
+    /// Forget a transaction, after its [`linger`](Self::linger) when it
+    /// sent a final response
+    fn complete(self: &Arc<Self>, key: TransactionKey, answered: bool, transport: TransportProtocol) {
+        let linger = self.linger(key.2, transport);
+        if !answered || linger.is_zero() {
+            self.transactions.lock().unwrap().remove(&key);
+            return;
         }
+        let dispatcher = self.clone();
+        tokio::spawn(async move {
+            tokio::time::sleep(linger).await;
+            dispatcher.transactions.lock().unwrap().remove(&key);
+        });
     }
 }
 
-use super::transport::TlsTransport;
+/// Where the responses of a server transaction go
+enum ResponsePath {
+    Udp {
+        socket: Arc<UdpSocket>,
+        destination: SocketAddr,
+    },
+    /// Back on the connection the request arrived on
+    Tcp {
+        connections: Arc<ConnectionTable>,
+        source: SocketAddr,
+    },
+    /// Responses are not written back over TLS yet
+    Tls,
+}
+
+impl ResponsePath {
+    fn transport(&self) -> TransportProtocol {
+        match self {
+            ResponsePath::Udp { .. } => TransportProtocol::Udp,
+            ResponsePath::Tcp { .. } => TransportProtocol::Tcp,
+            ResponsePath::Tls => TransportProtocol::Tls,
+        }
+    }
+
+    async fn send(&self, response: &SipResponse) {
+        match self {
+            ResponsePath::Udp { socket, destination } => {
+                if let Err(e) = socket.send_to(&response.to_bytes(), destination).await {
+                    error!("Failed to send response: {}", e);
+                }
+            }
+            ResponsePath::Tcp { connections, source } => {
+                match connections.writer_for(source).await {
+                    Some(writer) => {
+                        if let Err(e) = writer.send(response.to_bytes()).await {
+                            error!("Failed to send response via TCP: {}", e);
+                        }
+                    }
+                    None => {
+                        warn!("Connection to {} closed before response could be sent", source);
+                    }
+                }
+            }
+            ResponsePath::Tls => debug!("Response generated: {}", response.status_code()),
+        }
+    }
+}
 
 /// SIP server
 pub struct SipServer {
     config: SipServerConfig,
-    udp_transport: Option<UdpTransport>,
-    tcp_transport: Option<TcpTransport>,
-    tls_transport: Option<TlsTransport>,
-    handlers: Arc<RwLock<HashMap<SipMethod, Arc<dyn SipHandler>>>>,
+    /// Started listeners, draining ones included
+    listeners: Listeners,
+    /// Transaction users by method and their server transactions
+    dispatcher: Arc<Dispatcher>,
+    /// Per-device workarounds applied to requests and responses
+    quirks: Arc<QuirksRegistry>,
+    /// Server-originated requests (e.g. NOTIFY), sent once started
+    outbound_tx: mpsc::Sender<OutgoingMessage>,
+    outbound_rx: Option<mpsc::Receiver<OutgoingMessage>>,
+    /// Responses received to server-originated requests
+    responses: broadcast::Sender<SipResponse>,
+    /// Answers requests that looped back to us with 482
+    hops: Option<Arc<HopTracker>>,
+    /// Inbound header rules of trunks and routes
+    header_rules: Option<Arc<HeaderRulesEngine>>,
+    /// Per-call trace spans the transactions are recorded under
+    call_spans: Option<Arc<CallSpans>>,
 }
 
 impl SipServer {
     pub fn new(config: SipServerConfig) -> Self {
+        let (outbound_tx, outbound_rx) = mpsc::channel(1000);
+        let dispatcher = Arc::new(Dispatcher {
+            timers: config.timers.clone(),
+            ..Default::default()
+        });
         Self {
-            config: config.clone(),
-            udp_transport: Some(UdpTransport::new(config.udp_bind)),
-            tcp_transport: if config.enable_tcp {
-                Some(TcpTransport::new(config.tcp_bind))
-            } else {
-                None
-            },
-            tls_transport: if config.enable_tls {
-                Some(TlsTransport::new(
-                    config.tls_bind,
-                    config.tls_cert_path.clone(),
-                    config.tls_key_path.clone(),
-                ))
-            } else {
-                None
-            },
-            handlers: Arc::new(RwLock::new(HashMap::new())),
+            config,
+            listeners: Arc::new(StdRwLock::new(Vec::new())),
+            dispatcher,
+            quirks: Arc::new(QuirksRegistry::default()),
+            outbound_tx,
+            outbound_rx: Some(outbound_rx),
+            responses: broadcast::channel(256).0,
+            hops: None,
+            header_rules: None,
+            call_spans: None,
         }
     }
 
+    /// Interop quirk rules (defaults to the shipped rule set)
+    pub fn with_quirks(mut self, quirks: Arc<QuirksRegistry>) -> Self {
+        self.quirks = quirks;
+        self
+    }
+
+    /// Reject requests carrying a Via branch we issued with 482
+    pub fn with_hop_tracker(mut self, hops: Arc<HopTracker>) -> Self {
+        self.hops = Some(hops);
+        self
+    }
+
+    /// Apply the inbound header rules of trunks and routes to requests as
+    /// they arrive
+    /// Answer new dialogs with 503 while `overload` says so
+    pub fn with_overload_monitor(self, overload: Arc<OverloadMonitor>) -> Self {
+        if self.dispatcher.overload.set(overload).is_err() {
+            warn!("Overload monitor already set");
+        }
+        self
+    }
+
+    /// Counts the server transactions in progress or recently completed,
+    /// e.g. for overload sampling
+    pub fn transaction_counter(&self) -> impl Fn() -> usize + Send + Sync + 'static {
+        let dispatcher = self.dispatcher.clone();
+        move || dispatcher.transactions.lock().unwrap().len()
+    }
+
+    pub fn with_header_rules(mut self, header_rules: Arc<HeaderRulesEngine>) -> Self {
+        self.header_rules = Some(header_rules);
+        self
+    }
+
+    /// Record each received transaction as a child of its call's trace
+    /// span; takes effect on the listeners started afterwards
+    pub fn set_call_spans(&mut self, call_spans: Arc<CallSpans>) {
+        self.call_spans = Some(call_spans);
+    }
+
+    /// Sender for requests originated by the server
+    ///
+    /// UDP messages go out on the socket of the listener their dialog was
+    /// established on (else a listening socket); TCP messages reuse an open
+    /// connection to the destination.
+    pub fn outbound_sender(&self) -> mpsc::Sender<OutgoingMessage> {
+        self.outbound_tx.clone()
+    }
+
+    /// Release the dialog of every call that ends from the listeners and
+    /// connections carrying it
+    ///
+    /// A BYE received is handled as it arrives; this covers calls torn down
+    /// any other way, e.g. by a BYE we sent or on an outbound leg.
+    pub fn spawn_dialog_release(&self, bus: &dyn EventBus) -> JoinHandle<()> {
+        let listeners = self.listeners.clone();
+        let mut rx = bus.subscribe();
+        tokio::spawn(async move {
+            loop {
+                match rx.recv().await {
+                    Ok(envelope) if matches!(envelope.event, CallEvent::Ended(_)) => {
+                        Self::release_dialog(&listeners, &envelope.call_id).await;
+                    }
+                    Ok(_) => {}
+                    Err(broadcast::error::RecvError::Lagged(skipped)) => {
+                        warn!("Dropped {} call events (dialog release lagging)", skipped);
+                    }
+                    Err(broadcast::error::RecvError::Closed) => break,
+                }
+            }
+        })
+    }
+
+    /// Subscribe to responses received for server-originated requests
+    pub fn subscribe_responses(&self) -> broadcast::Receiver<SipResponse> {
+        self.responses.subscribe()
+    }
+
+    /// Register a handler answering each request with one final response
     pub async fn register_handler(&self, method: SipMethod, handler: Arc<dyn SipHandler>) {
-        let mut handlers = self.handlers.write().await;
+        self.register_transaction_user(method, Arc::new(SimpleTransactionUser::new(handler)))
+            .await;
+    }
+
+    /// Register a handler sending its responses through the transaction,
+    /// e.g. provisional responses before a deferred final one
+    pub async fn register_transaction_user(
+        &self,
+        method: SipMethod,
+        handler: Arc<dyn TransactionUser>,
+    ) {
+        let mut handlers = self.dispatcher.handlers.write().await;
         handlers.insert(method, handler);
         info!("Registered handler for SIP method: {}", method);
     }
 
+    /// Methods handlers are registered for
+    pub async fn registered_methods(&self) -> Vec<SipMethod> {
+        self.dispatcher.handlers.read().await.keys().copied().collect()
+    }
+
+    /// Local addresses of the started UDP listeners
+    pub fn udp_local_addrs(&self) -> Vec<SocketAddr> {
+        self.listeners()
+            .into_iter()
+            .filter(|l| l.spec.protocol == TransportProtocol::Udp)
+            .map(|l| l.local_addr)
+            .collect()
+    }
+
+    /// Started listeners, draining ones included
+    pub fn listeners(&self) -> Vec<ListenerInfo> {
+        self.listeners.read().unwrap().iter().map(Listener::info).collect()
+    }
+
+    /// Listeners `config` asks for
+    fn listener_specs(config: &SipServerConfig) -> Vec<ListenerSpec> {
+        let mut specs = vec![ListenerSpec::new(TransportProtocol::Udp, config.udp_bind)];
+        if let Some(bind) = config.udp6_bind {
+            specs.push(ListenerSpec::new(TransportProtocol::Udp, bind));
+        }
+        if config.enable_tcp {
+            specs.push(ListenerSpec::new(TransportProtocol::Tcp, config.tcp_bind));
+            if let Some(bind) = config.tcp6_bind {
+                specs.push(ListenerSpec::new(TransportProtocol::Tcp, bind));
+            }
+        }
+        if config.enable_tls {
+            specs.push(ListenerSpec::new(TransportProtocol::Tls, config.tls_bind));
+        }
+        specs
+    }
+
     pub async fn start(&mut self) -> Result<(), SipError> {
         info!("Starting SIP server");
         info!("Domain: {}", self.config.domain);
 
-        // Start UDP transport and get receiver
-        let mut udp_rx = None;
-        let udp_socket = if let Some(transport) = &mut self.udp_transport {
-            transport.start().await?;
-            info!("UDP transport started on {}", self.config.udp_bind);
-            udp_rx = Some(std::mem::replace(
-                transport.receiver(),
-                mpsc::channel(1).1,
-            ));
-            transport.socket.clone()
-        } else {
-            None
-        };
-
-        // Start TCP transport and get receiver
-        let mut tcp_rx = None;
-        if let Some(transport) = &mut self.tcp_transport {
-            transport.start().await?;
-            info!("TCP transport started on {}", self.config.tcp_bind);
-            tcp_rx = Some(std::mem::replace(
-                transport.receiver(),
-                mpsc::channel(1).1,
-            ));
-        }
-
-        // Start TLS transport and get receiver
-        let mut tls_rx = None;
-        if let Some(transport) = &mut self.tls_transport {
-            match transport.start().await {
-                Ok(_) => {
-                    info!("TLS transport started on {}", self.config.tls_bind);
-                    tls_rx = Some(std::mem::replace(
-                        transport.receiver(),
-                        mpsc::channel(1).1,
-                    ));
-                }
-                Err(e) => {
+        for spec in Self::listener_specs(&self.config) {
+            match self.start_listener(spec, &self.config).await {
+                Ok(_) => {}
+                Err(e) if spec.protocol == TransportProtocol::Tls => {
                     warn!("Failed to start TLS transport: {}. Continuing without TLS.", e);
                 }
+                Err(e) => return Err(e),
             }
         }
 
-        // Start message processing
-        if let Some(mut rx) = udp_rx {
-            let handlers = self.handlers.clone();
-            let socket = udp_socket;
+        // Send server-originated requests
+        if let Some(mut rx) = self.outbound_rx.take() {
+            let listeners = self.listeners.clone();
             tokio::spawn(async move {
-                while let Some(incoming) = rx.recv().await {
-                    let handlers = handlers.clone();
-                    let socket = socket.clone();
-                    tokio::spawn(async move {
-                        if let Err(e) = Self::process_udp_message(incoming, handlers, socket).await {
-                            error!("Error processing UDP message: {}", e);
-                        }
-                    });
+                while let Some(outgoing) = rx.recv().await {
+                    Self::send_outgoing(outgoing, &listeners).await;
                 }
             });
         }
 
-        if let Some(mut rx) = tcp_rx {
-            let handlers = self.handlers.clone();
-            tokio::spawn(async move {
-                while let Some(incoming) = rx.recv().await {
-                    let handlers = handlers.clone();
-                    tokio::spawn(async move {
-                        if let Err(e) = Self::process_tcp_message(incoming, handlers).await {
-                            error!("Error processing TCP message: {}", e);
+        info!("SIP server started successfully");
+        Ok(())
+    }
+
+    /// Start a listener while the server runs; returns its local address
+    pub async fn add_listener(&self, spec: ListenerSpec) -> Result<SocketAddr, SipError> {
+        self.start_listener(spec, &self.config).await
+    }
+
+    /// Drain a listener: it takes no new flows and closes once the dialogs
+    /// established on it end, or after the drain timeout
+    ///
+    /// Returns None when no active listener matches `spec`.
+    pub fn remove_listener(&self, spec: ListenerSpec) -> Option<ListenerOutcome> {
+        let (local_addr, dialogs) = {
+            let listeners = self.listeners.read().unwrap();
+            let listener = listeners
+                .iter()
+                .find(|l| l.spec == spec && !l.activity.is_draining())?;
+            listener.activity.set_draining(true);
+            (listener.local_addr, listener.activity.dialog_count())
+        };
+        info!(
+            "Draining {} listener on {} ({} dialogs)",
+            spec.protocol.as_str(),
+            local_addr,
+            dialogs
+        );
+        self.spawn_drain(spec);
+        Some(ListenerOutcome::Draining {
+            local_addr,
+            dialogs,
+        })
+    }
+
+    /// Bring the listeners in line with `config`
+    ///
+    /// New listeners are bound before the ones no longer configured start
+    /// draining, so the server keeps listening throughout. Listeners whose
+    /// address did not change (including draining ones configured again)
+    /// are kept. Media ports are not affected.
+    pub async fn reload_listeners(&self, config: &SipServerConfig) -> Vec<ListenerReport> {
+        let desired = Self::listener_specs(config);
+        let mut reports = Vec::new();
+
+        for spec in desired.iter().copied() {
+            let kept = {
+                let listeners = self.listeners.read().unwrap();
+                listeners.iter().find(|l| l.spec == spec).map(|listener| {
+                    // Configured again: stop draining it
+                    listener.activity.set_draining(false);
+                    listener.local_addr
+                })
+            };
+            let outcome = match kept {
+                Some(local_addr) => ListenerOutcome::Kept { local_addr },
+                None => match self.start_listener(spec, config).await {
+                    Ok(local_addr) => ListenerOutcome::Added { local_addr },
+                    Err(e) => {
+                        warn!(
+                            "Failed to add {} listener on {}: {}",
+                            spec.protocol.as_str(),
+                            spec.bind,
+                            e
+                        );
+                        ListenerOutcome::Failed {
+                            error: e.to_string(),
                         }
-                    });
-                }
-            });
+                    }
+                },
+            };
+            reports.push(ListenerReport { spec, outcome });
         }
 
-        if let Some(mut rx) = tls_rx {
-            let handlers = self.handlers.clone();
-            tokio::spawn(async move {
-                while let Some(incoming) = rx.recv().await {
-                    let handlers = handlers.clone();
-                    tokio::spawn(async move {
-                        if let Err(e) = Self::process_tls_message(incoming, handlers).await {
+        let removed: Vec<ListenerSpec> = self
+            .listeners()
+            .into_iter()
+            .filter(|l| l.state == ListenerState::Active && !desired.contains(&l.spec))
+            .map(|l| l.spec)
+            .collect();
+        for spec in removed {
+            if let Some(outcome) = self.remove_listener(spec) {
+                reports.push(ListenerReport { spec, outcome });
+            }
+        }
+
+        reports
+    }
+
+    /// Bind a listener and start processing its messages
+    async fn start_listener(
+        &self,
+        spec: ListenerSpec,
+        config: &SipServerConfig,
+    ) -> Result<SocketAddr, SipError> {
+        if self.listeners.read().unwrap().iter().any(|l| l.spec == spec) {
+            return Err(SipError::TransportError(format!(
+                "Already listening on {} {}",
+                spec.protocol.as_str(),
+                spec.bind
+            )));
+        }
+
+        let activity = Arc::new(ListenerActivity::default());
+        let (transport, route, local_addr) = match spec.protocol {
+            TransportProtocol::Udp => {
+                let mut transport = UdpTransport::new(spec.bind);
+                transport.start().await?;
+                let socket = transport.socket.clone().ok_or_else(|| {
+                    SipError::TransportError("UDP socket not bound".to_string())
+                })?;
+                let local_addr = socket
+                    .local_addr()
+                    .map_err(|e| SipError::TransportError(e.to_string()))?;
+                let rx = std::mem::replace(transport.receiver(), mpsc::channel(1).1);
+                self.spawn_udp_processing(rx, socket.clone(), activity.clone());
+                (
+                    ListenerTransport::Udp(transport),
+                    ListenerRoute::Udp(socket),
+                    local_addr,
+                )
+            }
+            TransportProtocol::Tcp => {
+                let mut transport = TcpTransport::with_connection_config(
+                    spec.bind,
+                    ConnectionConfig {
+                        idle_timeout: Duration::from_secs(config.tcp_idle_timeout_secs),
+                        max_connections: config.tcp_max_connections,
+                        ..Default::default()
+                    },
+                );
+                transport.start().await?;
+                let local_addr = transport.local_addr().ok_or_else(|| {
+                    SipError::TransportError("TCP listener not bound".to_string())
+                })?;
+                let connections = transport.connections();
+                let rx = std::mem::replace(transport.receiver(), mpsc::channel(1).1);
+                self.spawn_tcp_processing(rx, connections.clone(), activity.clone());
+                (
+                    ListenerTransport::Tcp(transport),
+                    ListenerRoute::Tcp(connections),
+                    local_addr,
+                )
+            }
+            TransportProtocol::Tls => {
+                let mut transport = TlsTransport::new(
+                    spec.bind,
+                    config.tls_cert_path.clone(),
+                    config.tls_key_path.clone(),
+                );
+                transport.start().await?;
+                let local_addr = transport.local_addr().ok_or_else(|| {
+                    SipError::TransportError("TLS listener not bound".to_string())
+                })?;
+                let rx = std::mem::replace(transport.receiver(), mpsc::channel(1).1);
+                self.spawn_tls_processing(rx, activity.clone());
+                (ListenerTransport::Tls(transport), ListenerRoute::None, local_addr)
+            }
+            other => {
+                return Err(SipError::TransportError(format!(
+                    "{} listeners are not supported",
+                    other.as_str()
+                )))
+            }
+        };
+
+        info!("{} transport started on {}", spec.protocol.as_str(), local_addr);
+        self.listeners.write().unwrap().push(Listener {
+            spec,
+            local_addr,
+            route,
+            activity,
+            transport,
+        });
+        Ok(local_addr)
+    }
+
+    /// Close a draining listener once it is idle or the drain timeout passed
+    fn spawn_drain(&self, spec: ListenerSpec) {
+        let listeners = self.listeners.clone();
+        let deadline =
+            tokio::time::Instant::now() + Duration::from_secs(self.config.listener_drain_timeout_secs);
+        tokio::spawn(async move {
+            loop {
+                let idle = listeners
+                    .read()
+                    .unwrap()
+                    .iter()
+                    .find(|l| l.spec == spec && l.activity.is_draining())
+                    .map(|l| l.activity.is_idle());
+                match idle {
+                    // Configured again, or already closed
+                    None => return,
+                    Some(true) => break,
+                    Some(false) if tokio::time::Instant::now() >= deadline => {
+                        warn!(
+                            "{} listener on {} still has dialogs at the drain timeout",
+                            spec.protocol.as_str(),
+                            spec.bind
+                        );
+                        break;
+                    }
+                    Some(false) => tokio::time::sleep(DRAIN_CHECK_INTERVAL).await,
+                }
+            }
+
+            let listener = {
+                let mut listeners = listeners.write().unwrap();
+                listeners
+                    .iter()
+                    .position(|l| l.spec == spec && l.activity.is_draining())
+                    .map(|i| listeners.remove(i))
+            };
+            if let Some(listener) = listener {
+                let local_addr = listener.local_addr;
+                match listener.close().await {
+                    Ok(()) => info!("{} listener on {} closed", spec.protocol.as_str(), local_addr),
+                    Err(e) => error!("Failed to close listener on {}: {}", local_addr, e),
+                }
+            }
+        });
+    }
+
+    fn spawn_udp_processing(
+        &self,
+        mut rx: mpsc::Receiver<IncomingMessage>,
+        socket: Arc<UdpSocket>,
+        activity: Arc<ListenerActivity>,
+    ) {
+        let dispatcher = self.dispatcher.clone();
+        let quirks = self.quirks.clone();
+        let responses = self.responses.clone();
+        let hops = self.hops.clone();
+        let header_rules = self.header_rules.clone();
+        let call_spans = self.call_spans.clone();
+        tokio::spawn(async move {
+            while let Some(mut incoming) = rx.recv().await {
+                Self::apply_header_rules(header_rules.as_deref(), &mut incoming);
+                let dispatcher = dispatcher.clone();
+                let quirks = quirks.clone();
+                let socket = socket.clone();
+                let responses = responses.clone();
+                let hops = hops.clone();
+                let activity = activity.clone();
+                let call_spans = call_spans.clone();
+                let span =
+                    Self::message_span(call_spans.as_deref(), header_rules.as_deref(), &incoming);
+                let call_id = incoming.message.call_id();
+                tokio::spawn(
+                    async move {
+                        if let Err(e) = Self::process_udp_message(
+                            incoming, dispatcher, quirks, socket, responses, hops, activity,
+                        )
+                        .await
+                        {
+                            error!("Error processing UDP message: {}", e);
+                        }
+                        Self::transaction_done(call_spans.as_deref(), call_id.as_deref()).await;
+                    }
+                    .instrument(span),
+                );
+            }
+        });
+    }
+
+    fn spawn_tcp_processing(
+        &self,
+        mut rx: mpsc::Receiver<IncomingMessage>,
+        connections: Arc<ConnectionTable>,
+        activity: Arc<ListenerActivity>,
+    ) {
+        let dispatcher = self.dispatcher.clone();
+        let quirks = self.quirks.clone();
+        let responses = self.responses.clone();
+        let hops = self.hops.clone();
+        let header_rules = self.header_rules.clone();
+        let call_spans = self.call_spans.clone();
+        tokio::spawn(async move {
+            while let Some(mut incoming) = rx.recv().await {
+                Self::apply_header_rules(header_rules.as_deref(), &mut incoming);
+                let dispatcher = dispatcher.clone();
+                let quirks = quirks.clone();
+                let connections = connections.clone();
+                let responses = responses.clone();
+                let hops = hops.clone();
+                let activity = activity.clone();
+                let call_spans = call_spans.clone();
+                let span =
+                    Self::message_span(call_spans.as_deref(), header_rules.as_deref(), &incoming);
+                let call_id = incoming.message.call_id();
+                tokio::spawn(
+                    async move {
+                        if let Err(e) = Self::process_tcp_message(
+                            incoming,
+                            dispatcher,
+                            quirks,
+                            connections,
+                            responses,
+                            hops,
+                            activity,
+                        )
+                        .await
+                        {
+                            error!("Error processing TCP message: {}", e);
+                        }
+                        Self::transaction_done(call_spans.as_deref(), call_id.as_deref()).await;
+                    }
+                    .instrument(span),
+                );
+            }
+        });
+    }
+
+    fn spawn_tls_processing(
+        &self,
+        mut rx: mpsc::Receiver<IncomingMessage>,
+        activity: Arc<ListenerActivity>,
+    ) {
+        let dispatcher = self.dispatcher.clone();
+        let quirks = self.quirks.clone();
+        let responses = self.responses.clone();
+        let hops = self.hops.clone();
+        let header_rules = self.header_rules.clone();
+        let call_spans = self.call_spans.clone();
+        tokio::spawn(async move {
+            while let Some(mut incoming) = rx.recv().await {
+                Self::apply_header_rules(header_rules.as_deref(), &mut incoming);
+                let dispatcher = dispatcher.clone();
+                let quirks = quirks.clone();
+                let responses = responses.clone();
+                let hops = hops.clone();
+                let activity = activity.clone();
+                let call_spans = call_spans.clone();
+                let span =
+                    Self::message_span(call_spans.as_deref(), header_rules.as_deref(), &incoming);
+                let call_id = incoming.message.call_id();
+                tokio::spawn(
+                    async move {
+                        if let Err(e) = Self::process_tls_message(
+                            incoming, dispatcher, quirks, responses, hops, activity,
+                        )
+                        .await
+                        {
                             error!("Error processing TLS message: {}", e);
                         }
-                    });
+                        Self::transaction_done(call_spans.as_deref(), call_id.as_deref()).await;
+                    }
+                    .instrument(span),
+                );
+            }
+        });
+    }
+
+    /// Routes a server-originated message can leave on
+    fn outbound_routes(listeners: &Listeners, call_id: Option<&str>) -> Vec<OutboundRoute> {
+        listeners
+            .read()
+            .unwrap()
+            .iter()
+            .map(|l| OutboundRoute {
+                route: l.route.clone(),
+                local_addr: l.local_addr,
+                draining: l.activity.is_draining(),
+                has_dialog: call_id.is_some_and(|call_id| l.activity.has_dialog(call_id)),
+            })
+            .collect()
+    }
+
+    async fn send_outgoing(outgoing: OutgoingMessage, listeners: &Listeners) {
+        let call_id = SipRequest::parse(&outgoing.data)
+            .ok()
+            .and_then(|request| request.call_id());
+        let mut routes = Self::outbound_routes(listeners, call_id.as_deref());
+        // The dialog's own listener first, then active listeners of the
+        // destination's address family
+        let v6 = outgoing.destination.is_ipv6();
+        routes.sort_by_key(|r| (!r.has_dialog, r.draining, r.local_addr.is_ipv6() != v6));
+
+        match outgoing.protocol {
+            TransportProtocol::Tcp => {
+                let mut writer = None;
+                for route in &routes {
+                    if let ListenerRoute::Tcp(connections) = &route.route {
+                        writer = connections.writer_for(&outgoing.destination).await;
+                        if writer.is_some() {
+                            break;
+                        }
+                    }
                 }
-            });
+                match writer {
+                    Some(writer) => {
+                        if let Err(e) = writer.send(outgoing.data).await {
+                            error!("Failed to send request via TCP: {}", e);
+                        }
+                    }
+                    None => warn!("No TCP connection to {}", outgoing.destination),
+                }
+            }
+            _ => {
+                let socket = routes.iter().find_map(|r| match &r.route {
+                    ListenerRoute::Udp(socket) => Some(socket),
+                    _ => None,
+                });
+                match socket {
+                    Some(sock) => {
+                        let destination = udp_destination(sock, outgoing.destination);
+                        if let Err(e) = sock.send_to(&outgoing.data, destination).await {
+                            error!("Failed to send request to {}: {}", outgoing.destination, e);
+                        }
+                    }
+                    None => warn!("UDP transport not started, dropping request"),
+                }
+            }
         }
-
-        info!("SIP server started successfully");
-        Ok(())
     }
 
     async fn process_udp_message(
         incoming: IncomingMessage,
-        handlers: Arc<RwLock<HashMap<SipMethod, Arc<dyn SipHandler>>>>,
-        socket: Option<Arc<tokio::net::UdpSocket>>,
+        dispatcher: Arc<Dispatcher>,
+        quirks: Arc<QuirksRegistry>,
+        socket: Arc<UdpSocket>,
+        responses: broadcast::Sender<SipResponse>,
+        hops: Option<Arc<HopTracker>>,
+        activity: Arc<ListenerActivity>,
     ) -> Result<(), SipError> {
         match incoming.message {
-            SipMessage::Request(request) => {
+            SipMessage::Request(mut request) => {
+                quirks.apply_to_request(&mut request);
                 let method = request.method();
                 debug!("Processing SIP request: {:?}", method);
 
-                let handlers = handlers.read().await;
-                if let Some(method) = method {
-                    if let Some(handler) = handlers.get(&method) {
-                        match handler.handle_request(request.clone()).await {
-                            Ok(response) => {
-                                if let Some(sock) = socket.as_ref() {
-                                    let data = response.to_bytes();
-                                    if let Err(e) = sock.send_to(&data, incoming.source).await {
-                                        error!("Failed to send response: {}", e);
-                                    }
-                                }
-                            }
-                            Err(e) => {
-                                error!("Handler error: {}", e);
-                                if let Some(sock) = socket.as_ref() {
-                                    if let Ok(error_response) =
-                                        ResponseBuilder::server_internal_error()
-                                            .build_for_request(&request)
-                                    {
-                                        let data = error_response.to_bytes();
-                                        let _ = sock.send_to(&data, incoming.source).await;
-                                    }
-                                }
-                            }
-                        }
-                    } else {
-                        warn!("No handler registered for method: {}", method);
-                        if let Some(sock) = socket.as_ref() {
-                            if let Ok(response) =
-                                ResponseBuilder::new(501).build_for_request(&request)
-                            {
-                                let data = response.to_bytes();
-                                let _ = sock.send_to(&data, incoming.source).await;
-                            }
+                // A draining listener only serves its dialogs and transactions
+                let call_id = request.call_id();
+                if !activity.accepts(call_id.as_deref()) {
+                    debug!("Listener draining, refusing new {:?} from {}", method, incoming.source);
+                    if method != Some(SipMethod::Ack) {
+                        if let Ok(response) = ResponseBuilder::new(503)
+                            .quirks(quirks.clone())
+                            .build_for_request(&request)
+                        {
+                            let _ = socket.send_to(&response.to_bytes(), incoming.source).await;
                         }
                     }
+                    return Ok(());
+                }
+                let _transaction = call_id.as_deref().map(|id| activity.begin_transaction(id));
+
+                if let Some(response) = Self::hop_rejection(hops.as_deref(), &request, &quirks) {
+                    let _ = socket.send_to(&response.to_bytes(), incoming.source).await;
+                    return Ok(());
+                }
+
+                if let Some(method) = method {
+                    let path = ResponsePath::Udp {
+                        socket,
+                        destination: incoming.source,
+                    };
+                    let response =
+                        Self::run_transaction(&dispatcher, method, request, &quirks, &path).await;
+                    if let (Some(response), Some(call_id)) = (&response, &call_id) {
+                        Self::track_dialog(&activity, method, response, call_id);
+                    }
                 }
             }
             SipMessage::Response(response) => {
                 debug!("Received SIP response: {}", response.status_code());
+                Self::track_answered_invite(&activity, &response);
+                // Nobody waiting for it is fine
+                let _ = responses.send(response);
             }
         }
 
@@ -236,29 +910,67 @@ impl SipServer {
 
     async fn process_tcp_message(
         incoming: IncomingMessage,
-        handlers: Arc<RwLock<HashMap<SipMethod, Arc<dyn SipHandler>>>>,
+        dispatcher: Arc<Dispatcher>,
+        quirks: Arc<QuirksRegistry>,
+        connections: Arc<ConnectionTable>,
+        responses: broadcast::Sender<SipResponse>,
+        hops: Option<Arc<HopTracker>>,
+        activity: Arc<ListenerActivity>,
     ) -> Result<(), SipError> {
         match incoming.message {
-            SipMessage::Request(request) => {
+            SipMessage::Request(mut request) => {
+                quirks.apply_to_request(&mut request);
                 let method = request.method();
                 debug!("Processing SIP request via TCP: {:?}", method);
+                // Connections already open are served even while draining
+                let _transaction = request
+                    .call_id()
+                    .map(|call_id| activity.begin_transaction(&call_id));
+
+                if let Some(response) = Self::hop_rejection(hops.as_deref(), &request, &quirks) {
+                    if let Some(writer) = connections.writer_for(&incoming.source).await {
+                        let _ = writer.send(response.to_bytes()).await;
+                    }
+                    return Ok(());
+                }
 
-                let handlers = handlers.read().await;
                 if let Some(method) = method {
-                    if let Some(handler) = handlers.get(&method) {
-                        match handler.handle_request(request).await {
-                            Ok(response) => {
-                                debug!("Response generated: {}", response.status_code());
+                    let path = ResponsePath::Tcp {
+                        connections: connections.clone(),
+                        source: incoming.source,
+                    };
+                    let call_id = request.call_id();
+                    let aliases = match method {
+                        SipMethod::Register => learned_aliases(&request),
+                        _ => Vec::new(),
+                    };
+                    let response =
+                        Self::run_transaction(&dispatcher, method, request, &quirks, &path).await;
+                    if let (Some(response), Some(call_id)) = (response, call_id) {
+                        match method {
+                            SipMethod::Invite if response.status_code() / 100 == 2 => {
+                                connections.attach_dialog(&incoming.source, &call_id).await;
                             }
-                            Err(e) => {
-                                error!("Handler error: {}", e);
+                            // The registrar accepted the credentials
+                            SipMethod::Register if response.status_code() / 100 == 2 => {
+                                for alias in aliases {
+                                    connections.bind_alias(alias, incoming.source).await;
+                                }
                             }
+                            SipMethod::Bye => connections.detach_dialog(&call_id).await,
+                            _ => {}
                         }
+                        Self::track_dialog(&activity, method, &response, &call_id);
                     }
                 }
             }
             SipMessage::Response(response) => {
                 debug!("Received SIP response via TCP: {}", response.status_code());
+                if let Some(call_id) = Self::track_answered_invite(&activity, &response) {
+                    connections.attach_dialog(&incoming.source, &call_id).await;
+                }
+                // Nobody waiting for it is fine
+                let _ = responses.send(response);
             }
         }
 
@@ -267,50 +979,255 @@ impl SipServer {
 
     async fn process_tls_message(
         incoming: IncomingMessage,
-        handlers: Arc<RwLock<HashMap<SipMethod, Arc<dyn SipHandler>>>>,
+        dispatcher: Arc<Dispatcher>,
+        quirks: Arc<QuirksRegistry>,
+        responses: broadcast::Sender<SipResponse>,
+        hops: Option<Arc<HopTracker>>,
+        activity: Arc<ListenerActivity>,
     ) -> Result<(), SipError> {
         match incoming.message {
-            SipMessage::Request(request) => {
+            SipMessage::Request(mut request) => {
+                quirks.apply_to_request(&mut request);
                 let method = request.method();
                 debug!("Processing SIP request via TLS: {:?}", method);
+                let _transaction = request
+                    .call_id()
+                    .map(|call_id| activity.begin_transaction(&call_id));
+
+                if Self::hop_rejection(hops.as_deref(), &request, &quirks).is_some() {
+                    return Ok(());
+                }
 
-                let handlers = handlers.read().await;
                 if let Some(method) = method {
-                    if let Some(handler) = handlers.get(&method) {
-                        match handler.handle_request(request).await {
-                            Ok(response) => {
-                                debug!("Response generated: {}", response.status_code());
-                            }
-                            Err(e) => {
-                                error!("Handler error: {}", e);
-                            }
-                        }
-                    } else {
-                        warn!("No handler registered for method: {}", method);
-                    }
+                    Self::run_transaction(&dispatcher, method, request, &quirks, &ResponsePath::Tls)
+                        .await;
                 }
             }
             SipMessage::Response(response) => {
                 debug!("Received SIP response via TLS: {}", response.status_code());
+                Self::track_answered_invite(&activity, &response);
+                // Nobody waiting for it is fine
+                let _ = responses.send(response);
             }
         }
 
         Ok(())
     }
 
-    pub async fn stop(&mut self) -> Result<(), SipError> {
-        info!("Stopping SIP server");
+    /// Run the server transaction of `request`: hand it to its transaction
+    /// user and transmit the responses the user sends, up to the final one,
+    /// which is returned
+    ///
+    /// A retransmitted request is answered with the latest response of its
+    /// transaction instead. ACK gets no response.
+    async fn run_transaction(
+        dispatcher: &Arc<Dispatcher>,
+        method: SipMethod,
+        request: SipRequest,
+        quirks: &Arc<QuirksRegistry>,
+        path: &ResponsePath,
+    ) -> Option<SipResponse> {
+        let key = match method {
+            SipMethod::Ack => None,
+            _ => extract_branch(request.headers())
+                .zip(request.call_id())
+                .map(|(branch, call_id)| (branch, call_id, method)),
+        };
+        if let Some(key) = &key {
+            if let Some(latest) = dispatcher.begin(key) {
+                debug!("Retransmitted {} answered by its transaction", method);
+                if let Some(response) = latest {
+                    path.send(&response).await;
+                }
+                return None;
+            }
+        }
+
+        // Under overload new dialogs are refused; everything else is served
+        if let Some(retry_after) = dispatcher.overload.get().and_then(|o| o.admit(&request)) {
+            debug!("Overloaded, refusing new {} with 503", method);
+            let response = ResponseBuilder::new(503)
+                .header(Header::Other("Retry-After".to_string(), retry_after.to_string()))
+                .quirks(quirks.clone())
+                .build_for_request(&request)
+                .ok();
+            if let Some(response) = &response {
+                path.send(response).await;
+            }
+            if let Some(key) = key {
+                if let Some(response) = &response {
+                    dispatcher.record(&key, response);
+                }
+                dispatcher.complete(key, response.is_some(), path.transport());
+            }
+            return response;
+        }
+
+        let (transaction, mut responses) = TransactionHandle::new(request.clone());
+        let handler = dispatcher.handler(method).await;
+        let dispatch = {
+            let quirks = quirks.clone();
+            async move {
+                let request = transaction.request();
+                let action = match handler {
+                    Some(handler) => handler.handle_transaction(transaction.clone()).await,
+                    None => {
+                        warn!("No handler registered for method: {}", method);
+                        ResponseBuilder::new(501)
+                            .quirks(quirks.clone())
+                            .build_for_request(request)
+                            .map(TransactionAction::Respond)
+                    }
+                };
+                match action {
+                    Ok(TransactionAction::Respond(response)) => {
+                        if let Err(e) = transaction.respond(response) {
+                            warn!("Response of the {} handler dropped: {}", method, e);
+                        }
+                    }
+                    Ok(TransactionAction::Defer) | Ok(TransactionAction::Absorb) => {}
+                    Err(e) => {
+                        error!("Handler error: {}", e);
+                        if !transaction.is_completed() {
+                            if let Ok(response) = ResponseBuilder::server_internal_error()
+                                .quirks(quirks.clone())
+                                .build_for_request(request)
+                            {
+                                let _ = transaction.respond(response);
+                            }
+                        }
+                    }
+                }
+            }
+        };
+        // Runs alongside the handler, so provisional responses go out
+        // while it works; deferred responses keep it waiting
+        let transmit = async {
+            while let Some(mut response) = responses.recv().await {
+                quirks.apply_to_response(&request, &mut response);
+                if method != SipMethod::Ack {
+                    path.send(&response).await;
+                }
+                if let Some(key) = &key {
+                    dispatcher.record(key, &response);
+                }
+                if response.status_code() >= 200 {
+                    return Some(response);
+                }
+            }
+            None
+        };
+        let ((), response) = tokio::join!(dispatch, transmit);
+
+        if response.is_none() && method != SipMethod::Ack {
+            warn!("{} transaction ended without a final response", method);
+        }
+        if let Some(key) = key {
+            dispatcher.complete(key, response.is_some(), path.transport());
+        }
+        response
+    }
+
+    /// Record dialogs established and ended over a listener
+    fn track_dialog(
+        activity: &ListenerActivity,
+        method: SipMethod,
+        response: &SipResponse,
+        call_id: &str,
+    ) {
+        match method {
+            SipMethod::Invite if response.status_code() / 100 == 2 => {
+                activity.attach_dialog(call_id)
+            }
+            SipMethod::Bye => activity.detach_dialog(call_id),
+            _ => {}
+        }
+    }
+
+    /// Record a dialog we established over a listener (an outbound INVITE
+    /// answered), returning its Call-ID
+    fn track_answered_invite(activity: &ListenerActivity, response: &SipResponse) -> Option<String> {
+        if response.cseq_method() != Some(SipMethod::Invite) || response.status_code() / 100 != 2 {
+            return None;
+        }
+        let call_id = response.call_id()?;
+        activity.attach_dialog(&call_id);
+        Some(call_id)
+    }
 
-        if let Some(transport) = &mut self.udp_transport {
-            transport.stop().await?;
+    /// Release a call's dialog from the listeners and connections carrying it
+    async fn release_dialog(listeners: &Listeners, call_id: &str) {
+        let tables: Vec<Arc<ConnectionTable>> = listeners
+            .read()
+            .unwrap()
+            .iter()
+            .filter_map(|listener| {
+                listener.activity.detach_dialog(call_id);
+                match &listener.route {
+                    ListenerRoute::Tcp(table) => Some(table.clone()),
+                    _ => None,
+                }
+            })
+            .collect();
+        for table in tables {
+            table.detach_dialog(call_id).await;
         }
+    }
 
-        if let Some(transport) = &mut self.tcp_transport {
-            transport.stop().await?;
+    /// Span of a received message: the Call-ID of its events, e.g. for
+    /// per-call debug logging, under the call's trace span when tracing
+    fn message_span(
+        call_spans: Option<&CallSpans>,
+        header_rules: Option<&HeaderRulesEngine>,
+        incoming: &IncomingMessage,
+    ) -> Span {
+        if let Some(call_spans) = call_spans {
+            let source = incoming.source.ip().to_string();
+            let trunk = header_rules.and_then(|rules| rules.trunk_of(&source));
+            return call_spans.transaction_span(&incoming.message, trunk);
+        }
+        match incoming.message.call_id() {
+            Some(call_id) => info_span!("sip", call_id = %call_id),
+            None => info_span!("sip"),
         }
+    }
+
+    /// Close the call's trace span when the transaction did not set up a
+    /// call
+    async fn transaction_done(call_spans: Option<&CallSpans>, call_id: Option<&str>) {
+        if let (Some(call_spans), Some(call_id)) = (call_spans, call_id) {
+            call_spans.transaction_done(call_id).await;
+        }
+    }
+
+    /// Run the inbound header rules on a received request
+    fn apply_header_rules(header_rules: Option<&HeaderRulesEngine>, incoming: &mut IncomingMessage) {
+        if let (Some(header_rules), SipMessage::Request(request)) =
+            (header_rules, &mut incoming.message)
+        {
+            header_rules.apply_inbound(request, incoming.source.ip());
+        }
+    }
+
+    /// Response for a request that looped back to us
+    fn hop_rejection(
+        hops: Option<&HopTracker>,
+        request: &SipRequest,
+        quirks: &Arc<QuirksRegistry>,
+    ) -> Option<SipResponse> {
+        let status = hops?.check(request)?;
+        ResponseBuilder::new(status)
+            .quirks(quirks.clone())
+            .build_for_request(request)
+            .ok()
+    }
+
+    pub async fn stop(&mut self) -> Result<(), SipError> {
+        info!("Stopping SIP server");
 
-        if let Some(transport) = &mut self.tls_transport {
-            transport.stop().await?;
+        let listeners = std::mem::take(&mut *self.listeners.write().unwrap());
+        for listener in listeners {
+            listener.close().await?;
         }
 
         info!("SIP server stopped");
@@ -329,9 +1246,628 @@ mod tests {
             tcp_bind: "127.0.0.1:0".parse().unwrap(),
             domain: "test.com".to_string(),
             enable_tcp: false,
+            ..Default::default()
         };
 
         let server = SipServer::new(config);
         assert_eq!(server.config.domain, "test.com");
     }
+
+    #[tokio::test]
+    async fn test_transaction_linger_follows_t1() {
+        let config = SipServerConfig {
+            timers: SipTimerConfig {
+                t1_ms: 10,
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let dispatcher = SipServer::new(config).dispatcher.clone();
+
+        let options_udp = dispatcher.linger(SipMethod::Options, TransportProtocol::Udp);
+        assert_eq!(options_udp, Duration::from_millis(640));
+        let invite_tcp = dispatcher.linger(SipMethod::Invite, TransportProtocol::Tcp);
+        assert_eq!(invite_tcp, Duration::from_millis(640));
+        assert!(dispatcher.linger(SipMethod::Options, TransportProtocol::Tcp).is_zero());
+        let default = Dispatcher::default().linger(SipMethod::Options, TransportProtocol::Udp);
+        assert_eq!(default, Duration::from_secs(32));
+
+        // Retransmissions are answered until Timer J, then the key is new
+        let key = ("z9hG4bKlinger".to_string(), "linger@test".to_string(), SipMethod::Options);
+        assert!(dispatcher.begin(&key).is_none());
+        dispatcher.complete(key.clone(), true, TransportProtocol::Udp);
+        assert!(dispatcher.begin(&key).is_some());
+        tokio::time::sleep(Duration::from_millis(900)).await;
+        assert!(dispatcher.begin(&key).is_none());
+    }
+
+    #[tokio::test]
+    async fn test_mwi_subscribe_through_server() {
+        use super::super::mwi_notifier::MwiNotifier;
+        use super::super::registrar::Registrar;
+        use crate::domain::voicemail::{VoicemailMessage, VoicemailRepository};
+        use crate::infrastructure::persistence::MemoryVoicemailRepository;
+        use tokio::net::UdpSocket;
+
+        // A free port for the server to listen on
+        let server_addr = std::net::UdpSocket::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap();
+        let config = SipServerConfig {
+            udp_bind: server_addr,
+            enable_tcp: false,
+            ..Default::default()
+        };
+        let mut server = SipServer::new(config);
+        server.start().await.unwrap();
+        let voicemail = Arc::new(MemoryVoicemailRepository::new());
+        voicemail
+            .create_message(VoicemailMessage::new(
+                "bob".to_string(),
+                "sip:carol@example.com".to_string(),
+                None,
+                12,
+                "vm.wav".to_string(),
+                "wav".to_string(),
+            ))
+            .await
+            .unwrap();
+        let notifier = MwiNotifier::new(
+            Arc::new(Registrar::new()),
+            voicemail,
+            server.outbound_sender(),
+            "example.com".to_string(),
+            server_addr.to_string(),
+        );
+        server.register_handler(SipMethod::Subscribe, Arc::new(notifier)).await;
+
+        let phone = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let subscribe = format!(
+            "SUBSCRIBE sip:bob@example.com SIP/2.0\r\n\
+             Via: SIP/2.0/UDP {addr};branch=z9hG4bKmwisub\r\n\
+             Max-Forwards: 70\r\n\
+             From: <sip:bob@example.com>;tag=phone1\r\n\
+             To: <sip:bob@example.com>\r\n\
+             Call-ID: mwi-server-sub\r\n\
+             CSeq: 1 SUBSCRIBE\r\n\
+             Contact: <sip:bob@{addr}>\r\n\
+             Event: message-summary\r\n\
+             Expires: 600\r\n\
+             Content-Length: 0\r\n\r\n",
+            addr = phone.local_addr().unwrap()
+        );
+        phone.send_to(subscribe.as_bytes(), server_addr).await.unwrap();
+
+        // The 202 and the initial NOTIFY both reach the phone
+        let mut response = None;
+        let mut notify = None;
+        while response.is_none() || notify.is_none() {
+            let mut buf = vec![0u8; 4096];
+            let (len, _) = tokio::time::timeout(Duration::from_secs(2), phone.recv_from(&mut buf))
+                .await
+                .expect("nothing received from server")
+                .unwrap();
+            match SipMessage::parse(&buf[..len]).unwrap() {
+                SipMessage::Response(r) => response = Some(r),
+                SipMessage::Request(r) => notify = Some(r),
+            }
+        }
+        assert_eq!(response.unwrap().status_code(), 202);
+        let notify = notify.unwrap();
+        assert_eq!(notify.method(), Some(SipMethod::Notify));
+        assert!(String::from_utf8_lossy(notify.body()).contains("Messages-Waiting: yes"));
+
+        server.stop().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_register_and_call_over_ipv6() {
+        use super::super::call_handler::InviteHandler;
+        use super::super::message::SipResponse;
+        use super::super::registrar::Registrar;
+        use super::super::sdp::SdpSession;
+
+        let config = SipServerConfig {
+            udp_bind: "127.0.0.1:0".parse().unwrap(),
+            udp6_bind: Some("[::1]:0".parse().unwrap()),
+            enable_tcp: false,
+            ..Default::default()
+        };
+        let mut server = SipServer::new(config);
+        let registrar = Arc::new(Registrar::new());
+        let invite_handler = Arc::new(
+            InviteHandler::new(registrar.clone(), "127.0.0.1".parse().unwrap())
+                .with_local_ipv6("::1".parse().unwrap()),
+        );
+        let call_router = invite_handler.call_router();
+        server.register_handler(SipMethod::Register, registrar.clone()).await;
+        server.register_handler(SipMethod::Invite, invite_handler).await;
+        server.start().await.unwrap();
+
+        let server_v6 = server
+            .udp_local_addrs()
+            .into_iter()
+            .find(|a| a.is_ipv6())
+            .unwrap();
+        let phone = UdpSocket::bind("[::1]:0").await.unwrap();
+        let phone_addr = phone.local_addr().unwrap();
+
+        let exchange = |request: String| {
+            let phone = &phone;
+            async move {
+                phone.send_to(request.as_bytes(), server_v6).await.unwrap();
+                let mut buf = vec![0u8; 4096];
+                let (len, from) = tokio::time::timeout(
+                    Duration::from_secs(2),
+                    phone.recv_from(&mut buf),
+                )
+                .await
+                .expect("no response from server")
+                .unwrap();
+                assert_eq!(from, server_v6);
+                SipResponse::parse(&buf[..len]).unwrap()
+            }
+        };
+
+        let register = format!(
+            "REGISTER sip:example.com SIP/2.0\r\n\
+             Via: SIP/2.0/UDP [::1]:{port};branch=z9hG4bKv6reg\r\n\
+             Max-Forwards: 70\r\n\
+             From: <sip:bob@example.com>;tag=r1\r\n\
+             To: <sip:bob@example.com>\r\n\
+             Call-ID: v6-register\r\n\
+             CSeq: 1 REGISTER\r\n\
+             Contact: <sip:bob@[::1]:{port}>\r\n\
+             Expires: 3600\r\n\
+             Content-Length: 0\r\n\r\n",
+            port = phone_addr.port()
+        );
+        assert_eq!(exchange(register).await.status_code(), 200);
+        assert_eq!(
+            call_router.find_callee_contact("sip:bob@example.com").await,
+            Some(phone_addr)
+        );
+
+        let sdp = "v=0\r\no=alice 1 1 IN IP6 ::1\r\ns=-\r\nc=IN IP6 ::1\r\nt=0 0\r\n\
+                   m=audio 49170 RTP/AVP 0\r\na=rtpmap:0 PCMU/8000\r\n";
+        let invite = format!(
+            "INVITE sip:bob@example.com SIP/2.0\r\n\
+             Via: SIP/2.0/UDP [::1]:{port};branch=z9hG4bKv6inv\r\n\
+             Max-Forwards: 70\r\n\
+             From: <sip:alice@example.com>;tag=a1\r\n\
+             To: <sip:bob@example.com>\r\n\
+             Call-ID: v6-call\r\n\
+             CSeq: 1 INVITE\r\n\
+             Contact: <sip:alice@[::1]:{port}>\r\n\
+             Content-Type: application/sdp\r\n\
+             Content-Length: {len}\r\n\r\n{sdp}",
+            port = phone_addr.port(),
+            len = sdp.len(),
+            sdp = sdp
+        );
+        let response = exchange(invite).await;
+        assert_eq!(response.status_code(), 200);
+
+        let answer = SdpSession::parse(&String::from_utf8_lossy(response.body())).unwrap();
+        assert_eq!(answer.connection.address_type, "IP6");
+        assert_eq!(answer.audio_address(), Some("::1".parse().unwrap()));
+
+        server.stop().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_quirks_applied_to_matching_device() {
+        use super::super::message::SipResponse;
+        use super::super::options_handler::allow_header;
+        use super::super::quirks::{Quirk, QuirkRule};
+        use super::super::registrar::Registrar;
+        use rsip::headers::UntypedHeader;
+        use rsip::Header;
+
+        let config = SipServerConfig {
+            udp_bind: "127.0.0.1:0".parse().unwrap(),
+            enable_tcp: false,
+            ..Default::default()
+        };
+        let quirks = QuirksRegistry::new(vec![
+            QuirkRule::new("fake-phone", r"^FakePhone/").with(Quirk::AlwaysIncludeAllow),
+        ])
+        .unwrap();
+        let quirks = Arc::new(quirks);
+        let mut server = SipServer::new(config).with_quirks(quirks.clone());
+        server
+            .register_handler(SipMethod::Register, Arc::new(Registrar::new()))
+            .await;
+        quirks.set_allow(allow_header(&server.registered_methods().await));
+        server.start().await.unwrap();
+        let server_addr = server.udp_local_addrs()[0];
+        let phone = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+
+        let exchange = |method: &'static str, user_agent: &'static str| {
+            let phone = &phone;
+            async move {
+                let request = format!(
+                    "{method} sip:example.com SIP/2.0\r\n\
+                     Via: SIP/2.0/UDP 127.0.0.1:5062;branch=z9hG4bK{method}\r\n\
+                     Max-Forwards: 70\r\n\
+                     From: <sip:alice@example.com>;tag=q\r\n\
+                     To: <sip:alice@example.com>\r\n\
+                     Call-ID: quirks-{method}-{user_agent}\r\n\
+                     CSeq: 1 {method}\r\n\
+                     Contact: <sip:alice@127.0.0.1:5062>\r\n\
+                     User-Agent: {user_agent}\r\n\
+                     Content-Length: 0\r\n\r\n"
+                );
+                phone.send_to(request.as_bytes(), server_addr).await.unwrap();
+                let mut buf = vec![0u8; 4096];
+                let (len, _) =
+                    tokio::time::timeout(Duration::from_secs(2), phone.recv_from(&mut buf))
+                        .await
+                        .expect("no response from server")
+                        .unwrap();
+                SipResponse::parse(&buf[..len]).unwrap()
+            }
+        };
+        let allow = |response: &SipResponse| {
+            response.headers().iter().find_map(|h| match h {
+                Header::Allow(allow) => Some(allow.value().to_string()),
+                _ => None,
+            })
+        };
+
+        // Handler response and the server's own 501 both get Allow
+        let response = exchange("REGISTER", "FakePhone/1.0").await;
+        assert_eq!(response.status_code(), 200);
+        assert_eq!(allow(&response).as_deref(), Some("OPTIONS, REGISTER"));
+
+        let response = exchange("OPTIONS", "FakePhone/1.0").await;
+        assert_eq!(response.status_code(), 501);
+        assert_eq!(allow(&response).as_deref(), Some("OPTIONS, REGISTER"));
+
+        // Other devices are left alone
+        let response = exchange("REGISTER", "OtherPhone/1.0").await;
+        assert_eq!(allow(&response), None);
+
+        server.stop().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_message_stored_and_forwarded_through_server() {
+        use super::super::message::{SipRequest, SipResponse};
+        use super::super::message_handler::MessageHandler;
+        use super::super::registrar::Registrar;
+        use crate::domain::instant_messaging::MessageRepository;
+        use crate::infrastructure::persistence::MemoryMessageRepository;
+
+        let config = SipServerConfig {
+            udp_bind: "127.0.0.1:0".parse().unwrap(),
+            enable_tcp: false,
+            ..Default::default()
+        };
+        let mut server = SipServer::new(config);
+        let registrar = Arc::new(Registrar::new());
+        let repository = Arc::new(MemoryMessageRepository::new());
+        server.register_handler(SipMethod::Register, registrar.clone()).await;
+        server.start().await.unwrap();
+        let server_addr = server.udp_local_addrs()[0];
+        let handler = MessageHandler::new(
+            registrar,
+            repository.clone(),
+            server.outbound_sender(),
+            "example.com".to_string(),
+            server_addr.to_string(),
+        );
+        server.register_handler(SipMethod::Message, Arc::new(handler)).await;
+
+        let alice = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let bob = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        async fn receive(socket: &UdpSocket) -> Vec<u8> {
+            let mut buf = vec![0u8; 4096];
+            let (len, _) = tokio::time::timeout(Duration::from_secs(2), socket.recv_from(&mut buf))
+                .await
+                .expect("nothing received from server")
+                .unwrap();
+            buf.truncate(len);
+            buf
+        }
+        let message = |n: u32, body: &str| {
+            format!(
+                "MESSAGE sip:bob@example.com SIP/2.0\r\n\
+                 Via: SIP/2.0/UDP {addr};branch=z9hG4bKim{n}\r\n\
+                 Max-Forwards: 70\r\n\
+                 From: <sip:alice@example.com>;tag=im{n}\r\n\
+                 To: <sip:bob@example.com>\r\n\
+                 Call-ID: im-{n}\r\n\
+                 CSeq: 1 MESSAGE\r\n\
+                 Content-Type: text/plain\r\n\
+                 Content-Length: {len}\r\n\r\n{body}",
+                addr = alice.local_addr().unwrap(),
+                len = body.len(),
+            )
+        };
+
+        // Bob is offline: the MESSAGE is accepted and stored
+        alice.send_to(message(1, "Call me").as_bytes(), server_addr).await.unwrap();
+        let response = SipResponse::parse(&receive(&alice).await).unwrap();
+        assert_eq!(response.status_code(), 202);
+        let pending = repository.list_pending_for("bob").await.unwrap();
+        assert_eq!(pending.len(), 1);
+
+        // Once Bob registers, new MESSAGEs reach his device
+        let register = format!(
+            "REGISTER sip:example.com SIP/2.0\r\n\
+             Via: SIP/2.0/UDP {addr};branch=z9hG4bKimreg\r\n\
+             Max-Forwards: 70\r\n\
+             From: <sip:bob@example.com>;tag=r1\r\n\
+             To: <sip:bob@example.com>\r\n\
+             Call-ID: im-register\r\n\
+             CSeq: 1 REGISTER\r\n\
+             Contact: <sip:bob@{addr}>\r\n\
+             Expires: 3600\r\n\
+             Content-Length: 0\r\n\r\n",
+            addr = bob.local_addr().unwrap()
+        );
+        bob.send_to(register.as_bytes(), server_addr).await.unwrap();
+        let response = SipResponse::parse(&receive(&bob).await).unwrap();
+        assert_eq!(response.status_code(), 200);
+
+        alice.send_to(message(2, "Lunch?").as_bytes(), server_addr).await.unwrap();
+        let forwarded = SipRequest::parse(&receive(&bob).await).unwrap();
+        assert_eq!(forwarded.method(), Some(SipMethod::Message));
+        assert_eq!(forwarded.body(), b"Lunch?");
+
+        server.stop().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_listeners_rebind_without_dropping_dialogs() {
+        use super::super::call_handler::{ByeHandler, InviteHandler};
+        use super::super::registrar::Registrar;
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+        use tokio::net::TcpStream;
+
+        let config = SipServerConfig {
+            udp_bind: "127.0.0.1:0".parse().unwrap(),
+            tcp_bind: "127.0.0.1:0".parse().unwrap(),
+            enable_tcp: false,
+            ..Default::default()
+        };
+        let mut server = SipServer::new(config.clone());
+        let registrar = Arc::new(Registrar::new());
+        let invite_handler = Arc::new(InviteHandler::new(
+            registrar.clone(),
+            "127.0.0.1".parse().unwrap(),
+        ));
+        let call_router = invite_handler.call_router();
+        let bye_handler = Arc::new(ByeHandler::with_router(
+            Arc::new(RwLock::new(HashMap::new())),
+            call_router.clone(),
+        ));
+        server.register_handler(SipMethod::Register, registrar).await;
+        server.register_handler(SipMethod::Invite, invite_handler).await;
+        server.register_handler(SipMethod::Bye, bye_handler).await;
+        server.start().await.unwrap();
+        let udp_addr = server.udp_local_addrs()[0];
+
+        // Hot-add a TCP listener; the UDP one is left alone
+        let reports = server
+            .reload_listeners(&SipServerConfig {
+                enable_tcp: true,
+                ..config.clone()
+            })
+            .await;
+        assert_eq!(reports.len(), 2);
+        assert_eq!(
+            reports[0].outcome,
+            ListenerOutcome::Kept {
+                local_addr: udp_addr
+            }
+        );
+        let tcp_addr = match reports[1].outcome {
+            ListenerOutcome::Added { local_addr } => local_addr,
+            ref other => panic!("TCP listener not added: {:?}", other),
+        };
+
+        let request = |method: &str, call_id: &str, transport: &str, port: u16, cseq: u32| {
+            let sdp = "v=0\r\no=alice 1 1 IN IP4 127.0.0.1\r\ns=-\r\nc=IN IP4 127.0.0.1\r\n\
+                       t=0 0\r\nm=audio 49170 RTP/AVP 0\r\na=rtpmap:0 PCMU/8000\r\n";
+            let (from, to, body) = match method {
+                "REGISTER" => ("bob", "bob", ""),
+                "INVITE" => ("alice", "bob", sdp),
+                _ => ("alice", "bob", ""),
+            };
+            let content_type = if body.is_empty() {
+                ""
+            } else {
+                "Content-Type: application/sdp\r\n"
+            };
+            format!(
+                "{method} sip:{to}@example.com SIP/2.0\r\n\
+                 Via: SIP/2.0/{transport} 127.0.0.1:{port};branch=z9hG4bK{call_id}{cseq}\r\n\
+                 Max-Forwards: 70\r\n\
+                 From: <sip:{from}@example.com>;tag=f1\r\n\
+                 To: <sip:{to}@example.com>\r\n\
+                 Call-ID: {call_id}\r\n\
+                 CSeq: {cseq} {method}\r\n\
+                 Contact: <sip:{from}@127.0.0.1:{port}>\r\n\
+                 Expires: 3600\r\n\
+                 {content_type}\
+                 Content-Length: {len}\r\n\r\n{body}",
+                len = body.len()
+            )
+        };
+
+        let phone = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let phone_port = phone.local_addr().unwrap().port();
+        let udp_exchange = |request: String| {
+            let phone = &phone;
+            async move {
+                phone.send_to(request.as_bytes(), udp_addr).await.unwrap();
+                let mut buf = vec![0u8; 4096];
+                let (len, _) =
+                    tokio::time::timeout(Duration::from_secs(2), phone.recv_from(&mut buf))
+                        .await
+                        .expect("no response over UDP")
+                        .unwrap();
+                SipResponse::parse(&buf[..len]).unwrap()
+            }
+        };
+        async fn tcp_exchange(stream: &mut TcpStream, request: String) -> SipResponse {
+            stream.write_all(request.as_bytes()).await.unwrap();
+            let mut buf = vec![0u8; 4096];
+            let len = tokio::time::timeout(Duration::from_secs(2), stream.read(&mut buf))
+                .await
+                .expect("no response over TCP")
+                .unwrap();
+            SipResponse::parse(&buf[..len]).unwrap()
+        }
+
+        let register = request("REGISTER", "rebind-register", "UDP", phone_port, 1);
+        assert_eq!(udp_exchange(register).await.status_code(), 200);
+
+        // A call over the new TCP listener
+        let mut stream = TcpStream::connect(tcp_addr).await.unwrap();
+        let stream_port = stream.local_addr().unwrap().port();
+        let invite = request("INVITE", "rebind-tcp", "TCP", stream_port, 1);
+        assert_eq!(tcp_exchange(&mut stream, invite).await.status_code(), 200);
+        let bye = request("BYE", "rebind-tcp", "TCP", stream_port, 2);
+        assert_eq!(tcp_exchange(&mut stream, bye).await.status_code(), 200);
+
+        // A UDP dialog is up when the UDP listener is removed
+        let invite = request("INVITE", "rebind-udp", "UDP", phone_port, 1);
+        assert_eq!(udp_exchange(invite).await.status_code(), 200);
+        let outcome = server
+            .remove_listener(ListenerSpec::new(TransportProtocol::Udp, config.udp_bind))
+            .unwrap();
+        assert_eq!(
+            outcome,
+            ListenerOutcome::Draining {
+                local_addr: udp_addr,
+                dialogs: 1
+            }
+        );
+
+        // New flows are refused while the dialog carries on
+        let register = request("REGISTER", "rebind-late", "UDP", phone_port, 1);
+        assert_eq!(udp_exchange(register).await.status_code(), 503);
+        let bye = request("BYE", "rebind-udp", "UDP", phone_port, 2);
+        assert_eq!(udp_exchange(bye).await.status_code(), 200);
+        assert_eq!(call_router.active_call_count().await, 0);
+
+        // With its dialog over, the UDP listener closes
+        let deadline = tokio::time::Instant::now() + Duration::from_secs(2);
+        while server.udp_local_addrs().contains(&udp_addr) {
+            assert!(tokio::time::Instant::now() < deadline, "UDP listener not closed");
+            tokio::time::sleep(Duration::from_millis(50)).await;
+        }
+        let listeners = server.listeners();
+        assert_eq!(listeners.len(), 1);
+        assert_eq!(listeners[0].spec.protocol, TransportProtocol::Tcp);
+        assert_eq!(listeners[0].state, ListenerState::Active);
+
+        server.stop().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_dialog_released_when_call_ends_without_bye() {
+        use super::super::call_handler::InviteHandler;
+        use super::super::registrar::Registrar;
+        use crate::application::call::CallApplicationService;
+        use crate::domain::call::{CallDirection, EndReason};
+        use crate::infrastructure::messaging::InProcessEventBus;
+        use uuid::Uuid;
+
+        let config = SipServerConfig {
+            udp_bind: "127.0.0.1:0".parse().unwrap(),
+            enable_tcp: false,
+            ..Default::default()
+        };
+        let mut server = SipServer::new(config.clone());
+        let registrar = Arc::new(Registrar::new());
+        let invite_handler = Arc::new(InviteHandler::new(
+            registrar.clone(),
+            "127.0.0.1".parse().unwrap(),
+        ));
+        server.register_handler(SipMethod::Register, registrar).await;
+        server.register_handler(SipMethod::Invite, invite_handler).await;
+        server.start().await.unwrap();
+        let udp_addr = server.udp_local_addrs()[0];
+
+        let bus = Arc::new(InProcessEventBus::default());
+        let calls = CallApplicationService::new(bus.clone());
+        server.spawn_dialog_release(bus.as_ref());
+
+        let phone = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let port = phone.local_addr().unwrap().port();
+        let exchange = |method: &str, from: &str, call_id: &str, body: &str| {
+            let content_type = if body.is_empty() {
+                ""
+            } else {
+                "Content-Type: application/sdp\r\n"
+            };
+            let request = format!(
+                "{method} sip:bob@example.com SIP/2.0\r\n\
+                 Via: SIP/2.0/UDP 127.0.0.1:{port};branch=z9hG4bK{call_id}\r\n\
+                 Max-Forwards: 70\r\n\
+                 From: <sip:{from}@example.com>;tag=f1\r\n\
+                 To: <sip:bob@example.com>\r\n\
+                 Call-ID: {call_id}\r\n\
+                 CSeq: 1 {method}\r\n\
+                 Contact: <sip:{from}@127.0.0.1:{port}>\r\n\
+                 Expires: 3600\r\n\
+                 {content_type}\
+                 Content-Length: {len}\r\n\r\n{body}",
+                len = body.len()
+            );
+            let phone = &phone;
+            async move {
+                phone.send_to(request.as_bytes(), udp_addr).await.unwrap();
+                let mut buf = vec![0u8; 4096];
+                let (len, _) =
+                    tokio::time::timeout(Duration::from_secs(2), phone.recv_from(&mut buf))
+                        .await
+                        .expect("no response over UDP")
+                        .unwrap();
+                SipResponse::parse(&buf[..len]).unwrap().status_code()
+            }
+        };
+
+        assert_eq!(exchange("REGISTER", "bob", "release-register", "").await, 200);
+        let sdp = "v=0\r\no=alice 1 1 IN IP4 127.0.0.1\r\ns=-\r\nc=IN IP4 127.0.0.1\r\n\
+                   t=0 0\r\nm=audio 49170 RTP/AVP 0\r\na=rtpmap:0 PCMU/8000\r\n";
+        assert_eq!(exchange("INVITE", "alice", "release-call", sdp).await, 200);
+        calls
+            .start(
+                "release-call",
+                Uuid::new_v4(),
+                "sip:alice@example.com",
+                "sip:bob@example.com",
+                CallDirection::Internal,
+            )
+            .await
+            .unwrap();
+
+        let outcome = server
+            .remove_listener(ListenerSpec::new(TransportProtocol::Udp, config.udp_bind))
+            .unwrap();
+        assert_eq!(
+            outcome,
+            ListenerOutcome::Draining {
+                local_addr: udp_addr,
+                dialogs: 1
+            }
+        );
+
+        // The PBX tears the call down itself: no BYE reaches the listener
+        calls.end("release-call", EndReason::NormalClearing).await.unwrap();
+
+        let deadline = tokio::time::Instant::now() + Duration::from_secs(2);
+        while server.udp_local_addrs().contains(&udp_addr) {
+            assert!(tokio::time::Instant::now() < deadline, "UDP listener not closed");
+            tokio::time::sleep(Duration::from_millis(50)).await;
+        }
+
+        server.stop().await.unwrap();
+    }
 }
@@ -0,0 +1,460 @@
+//! SRTP re-keying of long calls
+//!
+//! Every packet protected under one SDES master key uses up a little more of
+//! its keystream, and security policy caps how long a key may stay in use
+//! (all-day conference bridges would otherwise keep one key for hours).
+//! [`SrtpRekeyer`] watches the legs of established calls whose media is
+//! protected with SRTP: once a leg's key has been in use for the re-key
+//! interval, a re-INVITE offers the same media with a new `a=crypto` key
+//! under the next tag. When the leg accepts, its stream switches to the new
+//! key and keeps decrypting packets protected with the old one for a short
+//! overlap, so packets in flight during the switch are not lost. Each
+//! rotation is recorded in the audit log.
+//!
+//! A rejected re-INVITE leaves the old key in place until the next
+//! interval; glare (491) retries after the RFC 3261 back-off.
+
+use super::call_router::{CallRouter, LegMedia};
+use super::call_state::CallLeg;
+use super::dialog::{LocalReinviteOutcome, ReinviteSender};
+use super::sdp::SdpSession;
+use crate::infrastructure::audit::AuditLogger;
+use crate::infrastructure::media::srtp::SrtpMasterKey;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::sync::broadcast;
+use tokio::task::JoinHandle;
+use tracing::{debug, info, warn};
+
+/// Delay before trying again when the re-INVITE got no final response
+const PENDING_RETRY: Duration = Duration::from_secs(5);
+
+/// When and how SRTP calls are re-keyed
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SrtpRekeyPolicy {
+    pub enabled: bool,
+    /// Seconds a master key is used before the leg is re-keyed
+    pub interval_secs: u64,
+    /// Milliseconds packets protected with the replaced key still decrypt
+    pub overlap_ms: u64,
+}
+
+impl Default for SrtpRekeyPolicy {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            interval_secs: 8 * 3600,
+            overlap_ms: 2000,
+        }
+    }
+}
+
+impl SrtpRekeyPolicy {
+    pub fn validate(&self) -> Result<(), String> {
+        if self.enabled && self.interval_secs < 60 {
+            return Err("interval_secs must be at least 60".to_string());
+        }
+        if self.overlap_ms == 0 || self.overlap_ms > 30_000 {
+            return Err("overlap_ms must be between 1 and 30000".to_string());
+        }
+        Ok(())
+    }
+
+    pub fn interval(&self) -> Duration {
+        Duration::from_secs(self.interval_secs)
+    }
+
+    pub fn overlap(&self) -> Duration {
+        Duration::from_millis(self.overlap_ms)
+    }
+}
+
+/// Published while re-keying calls
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum SrtpRekeyEvent {
+    /// The leg accepted the new key and its stream switched to it
+    Rotated {
+        call_id: String,
+        leg: String,
+        crypto_tag: u32,
+    },
+    /// The leg did not accept the new key; the old one stays in use
+    Failed {
+        call_id: String,
+        leg: String,
+        status: u16,
+    },
+}
+
+pub(super) fn leg_name(leg: &CallLeg) -> &'static str {
+    match leg {
+        CallLeg::Caller => "caller",
+        CallLeg::Callee => "callee",
+    }
+}
+
+/// Re-keys the SRTP media of calls that outlive the re-key interval
+pub struct SrtpRekeyer {
+    router: Arc<CallRouter>,
+    policy: SrtpRekeyPolicy,
+    sender: Option<Arc<dyn ReinviteSender>>,
+    audit: Option<Arc<AuditLogger>>,
+    /// When each leg's key is next due, keyed by call and leg
+    due: Mutex<HashMap<(String, &'static str), Instant>>,
+    /// Legs whose re-INVITE is outstanding
+    rotating: Mutex<HashSet<(String, &'static str)>>,
+    events: broadcast::Sender<SrtpRekeyEvent>,
+}
+
+impl SrtpRekeyer {
+    pub fn new(router: Arc<CallRouter>, policy: SrtpRekeyPolicy) -> Self {
+        let (events, _) = broadcast::channel(256);
+        Self {
+            router,
+            policy,
+            sender: None,
+            audit: None,
+            due: Mutex::new(HashMap::new()),
+            rotating: Mutex::new(HashSet::new()),
+            events,
+        }
+    }
+
+    /// Send the re-INVITEs; without one, keys are never rotated
+    pub fn with_sender(mut self, sender: Arc<dyn ReinviteSender>) -> Self {
+        self.sender = Some(sender);
+        self
+    }
+
+    /// Record each rotation in the audit log
+    pub fn with_audit_logger(mut self, audit: Arc<AuditLogger>) -> Self {
+        self.audit = Some(audit);
+        self
+    }
+
+    /// Rotation events
+    pub fn subscribe(&self) -> broadcast::Receiver<SrtpRekeyEvent> {
+        self.events.subscribe()
+    }
+
+    /// Check SRTP legs every `tick`
+    pub fn spawn(self: Arc<Self>, tick: Duration) -> JoinHandle<()> {
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(tick);
+            loop {
+                interval.tick().await;
+                self.check_at(Instant::now()).await;
+            }
+        })
+    }
+
+    /// Start the rotations due as of `now`
+    ///
+    /// A leg's interval starts when it is first seen with SRTP.
+    pub async fn check_at(self: &Arc<Self>, now: Instant) {
+        let media = self.router.established_media().await;
+        {
+            let mut due = self.due.lock().unwrap();
+            due.retain(|(call_id, leg), _| {
+                media
+                    .iter()
+                    .any(|m| &m.call_id == call_id && leg_name(&m.leg) == *leg)
+            });
+        }
+
+        for leg in media {
+            if !leg.stream.is_srtp_enabled().await {
+                continue;
+            }
+            let key = (leg.call_id.clone(), leg_name(&leg.leg));
+            {
+                let mut due = self.due.lock().unwrap();
+                let due_at = *due
+                    .entry(key.clone())
+                    .or_insert(now + self.policy.interval());
+                if now < due_at {
+                    continue;
+                }
+            }
+            if !self.rotating.lock().unwrap().insert(key.clone()) {
+                continue;
+            }
+
+            let rekeyer = self.clone();
+            tokio::spawn(async move {
+                let next = rekeyer.rotate(&leg).await;
+                rekeyer.due.lock().unwrap().insert(key.clone(), now + next);
+                rekeyer.rotating.lock().unwrap().remove(&key);
+            });
+        }
+    }
+
+    /// Offer a new key to the leg, returning the delay before its next
+    /// rotation
+    async fn rotate(&self, media: &LegMedia) -> Duration {
+        let interval = self.policy.interval();
+        let leg = leg_name(&media.leg);
+        let Some(sender) = &self.sender else {
+            warn!(
+                "No re-INVITE sender, SRTP key of call {} ({}) not rotated",
+                media.call_id, leg
+            );
+            return interval;
+        };
+
+        let dialogs = self.router.dialog_manager();
+        let owns_call_id = media.leg == CallLeg::Callee;
+        let offer = dialogs
+            .with_dialog(&media.call_id, owns_call_id, |d| {
+                d.last_local_sdp().and_then(SdpSession::parse)
+            })
+            .await;
+        let Some(mut offer) = offer else {
+            warn!("No SDP to re-key call {} ({})", media.call_id, leg);
+            return interval;
+        };
+        let Some((_, profile)) = offer.get_srtp_crypto() else {
+            debug!("Call {} ({}) offered no SDES key, not re-keying", media.call_id, leg);
+            return interval;
+        };
+
+        let master_key = SrtpMasterKey::generate(profile);
+        let Some(crypto_tag) = offer.rekey_srtp_crypto(&master_key, profile) else {
+            return interval;
+        };
+        let suite = offer
+            .audio_media()
+            .and_then(|audio| audio.crypto.first())
+            .map(|crypto| crypto.crypto_suite.clone())
+            .unwrap_or_default();
+
+        info!(
+            "Re-keying SRTP of call {} ({}) with crypto tag {}",
+            media.call_id, leg, crypto_tag
+        );
+        let Some((outcome, _)) = dialogs
+            .reoffer(&media.call_id, &media.leg, sender.as_ref(), offer)
+            .await
+        else {
+            return interval;
+        };
+        match outcome {
+            LocalReinviteOutcome::Completed => {
+                media
+                    .stream
+                    .rekey_srtp(master_key, profile, self.policy.overlap())
+                    .await;
+                if let Some(audit) = &self.audit {
+                    audit
+                        .log_srtp_key_rotation(
+                            media.call_id.clone(),
+                            leg.to_string(),
+                            crypto_tag,
+                            suite,
+                        )
+                        .await;
+                }
+                let _ = self.events.send(SrtpRekeyEvent::Rotated {
+                    call_id: media.call_id.clone(),
+                    leg: leg.to_string(),
+                    crypto_tag,
+                });
+                interval
+            }
+            LocalReinviteOutcome::RetryAfter(delay) => {
+                debug!("Re-INVITE of call {} still in glare, re-keying later", media.call_id);
+                delay
+            }
+            LocalReinviteOutcome::Pending => PENDING_RETRY,
+            LocalReinviteOutcome::Failed(status) => {
+                self.rekey_failed(media, status);
+                interval
+            }
+        }
+    }
+
+    fn rekey_failed(&self, media: &LegMedia, status: u16) {
+        warn!(
+            "Call {} ({}) refused the new SRTP key with {}, keeping the old one",
+            media.call_id,
+            leg_name(&media.leg),
+            status
+        );
+        let _ = self.events.send(SrtpRekeyEvent::Failed {
+            call_id: media.call_id.clone(),
+            leg: leg_name(&media.leg).to_string(),
+            status,
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::message::SipError;
+    use crate::infrastructure::audit::logger::{AuditEventType, AuditQuery, MemoryAuditBackend};
+    use crate::infrastructure::media::srtp::SrtpProfile;
+    use crate::infrastructure::media::MediaStream;
+    use crate::infrastructure::protocols::sip::registrar::Registrar;
+    use async_trait::async_trait;
+    use std::net::IpAddr;
+
+    /// Answers every re-INVITE with `status`, recording the offers
+    struct Answering {
+        status: u16,
+        offers: Mutex<Vec<String>>,
+    }
+
+    #[async_trait]
+    impl ReinviteSender for Answering {
+        async fn send_reinvite(
+            &self,
+            _call_id: &str,
+            _leg: &CallLeg,
+            _cseq: u32,
+            sdp: &str,
+        ) -> Result<(u16, Option<String>), SipError> {
+            self.offers.lock().unwrap().push(sdp.to_string());
+            let answer = (self.status < 300).then(|| sdp.to_string());
+            Ok((self.status, answer))
+        }
+    }
+
+    fn policy() -> SrtpRekeyPolicy {
+        SrtpRekeyPolicy {
+            enabled: true,
+            interval_secs: 3600,
+            ..Default::default()
+        }
+    }
+
+    /// Established call whose callee leg uses SRTP
+    async fn srtp_call(router: &CallRouter, port: u16) -> Arc<MediaStream> {
+        router
+            .create_call(
+                "call-srtp".to_string(),
+                "sip:alice@example.com".to_string(),
+                "sip:bob@example.com".to_string(),
+            )
+            .await
+            .unwrap();
+        router.answer_call("call-srtp").await.unwrap();
+
+        let profile = SrtpProfile::Aes128CmHmacSha1_80;
+        let master_key = SrtpMasterKey::generate(profile);
+        let stream = Arc::new(MediaStream::new(port, 0, 8000).await.unwrap());
+        stream.enable_srtp(master_key.clone(), profile).await;
+        router
+            .set_leg_stream("call-srtp", &CallLeg::Callee, stream.clone())
+            .await;
+
+        let ip: IpAddr = "127.0.0.1".parse().unwrap();
+        let mut local = SdpSession::create_audio_session(ip, port);
+        local.add_srtp_crypto(&master_key, profile);
+        let offer = local.to_string();
+        router
+            .dialog_manager()
+            .with_dialog("call-srtp", true, |d| d.answer_offer(1, &offer, local))
+            .await;
+        stream
+    }
+
+    async fn next_event(events: &mut broadcast::Receiver<SrtpRekeyEvent>) -> SrtpRekeyEvent {
+        tokio::time::timeout(Duration::from_secs(2), events.recv())
+            .await
+            .unwrap()
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_rotates_key_after_interval() {
+        let router = Arc::new(CallRouter::new(Arc::new(Registrar::new())));
+        let stream = srtp_call(&router, 10170).await;
+        let sender = Arc::new(Answering {
+            status: 200,
+            offers: Mutex::new(Vec::new()),
+        });
+        let audit = Arc::new(AuditLogger::new(Arc::new(MemoryAuditBackend::new(100))));
+        let rekeyer = Arc::new(
+            SrtpRekeyer::new(router.clone(), policy())
+                .with_sender(sender.clone())
+                .with_audit_logger(audit.clone()),
+        );
+        let mut events = rekeyer.subscribe();
+
+        let start = Instant::now();
+        rekeyer.check_at(start).await;
+        rekeyer.check_at(start + Duration::from_secs(1800)).await;
+        assert!(sender.offers.lock().unwrap().is_empty());
+
+        rekeyer.check_at(start + Duration::from_secs(3600)).await;
+        assert_eq!(
+            next_event(&mut events).await,
+            SrtpRekeyEvent::Rotated {
+                call_id: "call-srtp".to_string(),
+                leg: "callee".to_string(),
+                crypto_tag: 2,
+            }
+        );
+        let offer = sender.offers.lock().unwrap()[0].clone();
+        assert!(offer.contains("a=crypto:2 AES_CM_128_HMAC_SHA1_80 inline:"));
+        assert!(!offer.contains("a=crypto:1 "));
+        assert!(stream.is_srtp_enabled().await);
+
+        let logged = audit.query(AuditQuery::default()).await.unwrap();
+        assert!(matches!(
+            &logged[0].event_type,
+            AuditEventType::SrtpKeyRotated { call_id, crypto_tag: 2, .. } if call_id == "call-srtp"
+        ));
+
+        // The next rotation is a full interval later
+        rekeyer.check_at(start + Duration::from_secs(5400)).await;
+        rekeyer.check_at(start + Duration::from_secs(7200)).await;
+        assert!(matches!(
+            next_event(&mut events).await,
+            SrtpRekeyEvent::Rotated { crypto_tag: 3, .. }
+        ));
+        assert_eq!(sender.offers.lock().unwrap().len(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_refused_key_keeps_old_one() {
+        let router = Arc::new(CallRouter::new(Arc::new(Registrar::new())));
+        srtp_call(&router, 10180).await;
+        let sender = Arc::new(Answering {
+            status: 488,
+            offers: Mutex::new(Vec::new()),
+        });
+        let audit = Arc::new(AuditLogger::new(Arc::new(MemoryAuditBackend::new(100))));
+        let rekeyer = Arc::new(
+            SrtpRekeyer::new(router.clone(), policy())
+                .with_sender(sender)
+                .with_audit_logger(audit.clone()),
+        );
+        let mut events = rekeyer.subscribe();
+
+        let start = Instant::now();
+        rekeyer.check_at(start).await;
+        rekeyer.check_at(start + Duration::from_secs(3600)).await;
+        assert!(matches!(
+            next_event(&mut events).await,
+            SrtpRekeyEvent::Failed { status: 488, .. }
+        ));
+        assert!(audit.query(AuditQuery::default()).await.unwrap().is_empty());
+
+        // The dialog still offers the original key
+        let local = router
+            .dialog_manager()
+            .get("call-srtp")
+            .await
+            .unwrap()
+            .last_local_sdp()
+            .unwrap()
+            .to_string();
+        assert!(local.contains("a=crypto:1 "));
+    }
+}
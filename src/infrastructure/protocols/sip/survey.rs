@@ -0,0 +1,372 @@
+//! Post-call survey of a queue caller
+//!
+//! A call sampled for its queue's survey is armed in the router, so the
+//! agent's BYE releases only the agent's leg. The caller then hears the
+//! questions; the digits they press (RFC 2833 or INFO, through the call's
+//! DTMF stream) answer them. The response is stored when the survey ends,
+//! after which the PBX hangs up the caller. A caller who hangs up first
+//! closes the DTMF stream and leaves a partial response.
+
+use super::call_router::CallRouter;
+use super::transport::{OutgoingMessage, TransportProtocol};
+use crate::domain::call_announcer::{AnnouncementRequest, CallAnnouncer};
+use crate::domain::call_survey::{SurveyResponse, SurveyRun, SurveyService};
+use crate::infrastructure::ivr::{DtmfDispatcher, DtmfEvent};
+use bytes::Bytes;
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::Arc;
+use tokio::sync::{broadcast, mpsc};
+use tracing::{debug, info, warn};
+use uuid::Uuid;
+
+/// Runs the surveys of calls whose agent hung up
+pub struct SurveyRunner {
+    router: Arc<CallRouter>,
+    surveys: Arc<SurveyService>,
+    dtmf: Arc<DtmfDispatcher>,
+    announcer: Option<Arc<CallAnnouncer>>,
+    /// Where the caller's BYE goes out, with the address put in its Via
+    outbound: Option<(mpsc::Sender<OutgoingMessage>, String)>,
+    cseq: AtomicU32,
+}
+
+impl SurveyRunner {
+    pub fn new(router: Arc<CallRouter>, surveys: Arc<SurveyService>, dtmf: Arc<DtmfDispatcher>) -> Self {
+        Self {
+            router,
+            surveys,
+            dtmf,
+            announcer: None,
+            outbound: None,
+            cseq: AtomicU32::new(1),
+        }
+    }
+
+    /// Play the questions into calls (otherwise the answers are only awaited)
+    pub fn with_announcer(mut self, announcer: Arc<CallAnnouncer>) -> Self {
+        self.announcer = Some(announcer);
+        self
+    }
+
+    /// BYE the caller once the survey is over, with `local_addr` as the Via
+    /// sent-by (without, calls are only ended locally)
+    pub fn with_outbound(mut self, outbound: mpsc::Sender<OutgoingMessage>, local_addr: String) -> Self {
+        self.outbound = Some((outbound, local_addr));
+        self
+    }
+
+    /// A queue call was answered by `agent`; returns whether the caller
+    /// takes the queue's survey after the call
+    pub async fn offer(&self, call_id: &str, queue_id: Uuid, agent: Option<String>) -> bool {
+        if !self.surveys.offer(call_id, queue_id, agent) {
+            return false;
+        }
+        if let Err(e) = self.router.arm_survey(call_id).await {
+            warn!("Not surveying call {}: {}", call_id, e);
+            self.surveys.discard(call_id);
+            return false;
+        }
+        debug!("Call {} sampled for the survey of queue {}", call_id, queue_id);
+        true
+    }
+
+    /// A BYE from `from_uri` arrived for `call_id`; returns true when it
+    /// was the agent of a surveyed call, whose caller now takes the survey
+    pub async fn agent_left(self: &Arc<Self>, call_id: &str, from_uri: &str) -> bool {
+        if !self.surveys.is_pending(call_id) || !self.router.release_agent(call_id, from_uri).await {
+            return false;
+        }
+        let Some(call) = self.router.checkpoint(call_id).await else {
+            return false;
+        };
+        let Some(run) = self.surveys.start(call_id, &call.caller_uri) else {
+            return false;
+        };
+        // Subscribed before the caller can press or hang up
+        let digits = self.dtmf.subscribe(call_id);
+        let runner = self.clone();
+        tokio::spawn(async move {
+            runner.run(run.with_cdr_id(call.cdr_id), digits).await;
+        });
+        true
+    }
+
+    /// The caller of `call_id` hung up: a survey not started yet is
+    /// dropped and one in progress ends partial
+    pub fn caller_left(&self, call_id: &str) {
+        self.surveys.discard(call_id);
+        self.dtmf.remove(call_id);
+    }
+
+    /// Ask the questions of a survey and store the answers
+    pub async fn run(&self, mut run: SurveyRun, mut digits: broadcast::Receiver<DtmfEvent>) -> SurveyResponse {
+        let call_id = run.call_id().to_string();
+        let call_id = call_id.as_str();
+        let timeout = run.definition().answer_timeout();
+        info!("Surveying caller of call {} ({})", call_id, run.definition().name);
+
+        let mut caller_present = true;
+        'questions: while let Some(prompt) = run.prompt() {
+            self.play(prompt);
+            let deadline = tokio::time::Instant::now() + timeout;
+            let answered = loop {
+                match tokio::time::timeout_at(deadline, digits.recv()).await {
+                    Ok(Ok(event)) => break run.press(event.digit.to_char()),
+                    Ok(Err(broadcast::error::RecvError::Lagged(_))) => continue,
+                    Ok(Err(broadcast::error::RecvError::Closed)) => {
+                        info!("Caller of call {} left the survey", call_id);
+                        caller_present = false;
+                        run.abandon();
+                        break 'questions;
+                    }
+                    Err(_) => {
+                        // Gone without the stream closing
+                        if self.router.checkpoint(call_id).await.is_none() {
+                            caller_present = false;
+                            run.abandon();
+                            break 'questions;
+                        }
+                        break false;
+                    }
+                }
+            };
+            if !answered && !run.retry() {
+                debug!("Survey of call {} unanswered, giving up", call_id);
+            }
+        }
+
+        if let Some(prompt) = run.thanks_prompt() {
+            self.play(prompt);
+        }
+        let response = run.response();
+        self.surveys.record(response.clone());
+        info!(
+            "Survey of call {} {:?} with {} answers",
+            call_id,
+            response.status,
+            response.answers.len()
+        );
+
+        if caller_present {
+            self.send_bye(call_id).await;
+            if let Err(e) = self.router.terminate_call(call_id).await {
+                debug!("Call {} gone before the survey ended: {}", call_id, e);
+            }
+        }
+        self.dtmf.remove(call_id);
+        response
+    }
+
+    fn play(&self, prompt: AnnouncementRequest) {
+        if let Some(announcer) = &self.announcer {
+            let call_id = prompt.call_id.clone();
+            if let Err(e) = announcer.play_announcement(prompt) {
+                warn!("Failed to play survey prompt to call {}: {}", call_id, e);
+            }
+        }
+    }
+
+    /// BYE the caller, in the dialog we answered
+    async fn send_bye(&self, call_id: &str) {
+        let Some((outbound, local_addr)) = &self.outbound else {
+            return;
+        };
+        let Some(call) = self.router.checkpoint(call_id).await else {
+            return;
+        };
+        let Some(contact) = call.caller_contact else {
+            return;
+        };
+        let (caller_tag, local_tag) = self.router.dialog_tags(call_id).await.unwrap_or_default();
+        let tag = |tag: Option<String>| tag.map(|t| format!(";tag={}", t)).unwrap_or_default();
+        let bye = format!(
+            "BYE sip:{user}@{contact} SIP/2.0\r\n\
+             Via: SIP/2.0/UDP {local};branch=z9hG4bK{branch}\r\n\
+             Max-Forwards: 70\r\n\
+             From: <{from}>{from_tag}\r\n\
+             To: <{to}>{to_tag}\r\n\
+             Call-ID: {call_id}\r\n\
+             CSeq: {cseq} BYE\r\n\
+             Content-Length: 0\r\n\
+             \r\n",
+            user = CallRouter::extract_username(&call.caller_uri),
+            contact = contact,
+            local = local_addr,
+            branch = Uuid::new_v4().simple(),
+            from = call.callee_uri,
+            from_tag = tag(local_tag),
+            to = call.caller_uri,
+            to_tag = tag(caller_tag),
+            call_id = call_id,
+            cseq = match self.router.sequence_vault() {
+                Some(vault) => vault.next_cseq(call_id),
+                None => self.cseq.fetch_add(1, Ordering::Relaxed),
+            },
+        );
+        debug!("Sending survey BYE for call {} to {}", call_id, contact);
+        if let Err(e) = outbound
+            .send(OutgoingMessage {
+                data: Bytes::from(bye),
+                destination: contact,
+                protocol: TransportProtocol::Udp,
+            })
+            .await
+        {
+            warn!("Failed to queue BYE for call {}: {}", call_id, e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::domain::call_survey::{
+        SurveyAssignment, SurveyDefinition, SurveyPolicy, SurveyQuestion, SurveyStatus,
+    };
+    use crate::infrastructure::ivr::DtmfDigit;
+    use crate::infrastructure::protocols::sip::Registrar;
+    use std::time::Duration;
+
+    const CALLER: &str = "sip:+15559999@trunk.example.net";
+    const AGENT: &str = "sip:agent1@example.com";
+
+    struct Harness {
+        router: Arc<CallRouter>,
+        surveys: Arc<SurveyService>,
+        dtmf: Arc<DtmfDispatcher>,
+        runner: Arc<SurveyRunner>,
+        queue_id: Uuid,
+    }
+
+    fn harness() -> Harness {
+        let queue_id = Uuid::new_v4();
+        let surveys = Arc::new(SurveyService::new(SurveyPolicy {
+            surveys: vec![SurveyDefinition::new(
+                "csat",
+                vec![
+                    SurveyQuestion::new("survey_rate_agent", 1, 5),
+                    SurveyQuestion::new("survey_issue_solved", 1, 2),
+                ],
+            )],
+            assignments: vec![SurveyAssignment {
+                queue_id,
+                survey: "csat".to_string(),
+                sample_percent: 100,
+            }],
+        }));
+        let router = Arc::new(CallRouter::new(Arc::new(Registrar::new())));
+        let dtmf = Arc::new(DtmfDispatcher::new());
+        let runner = Arc::new(SurveyRunner::new(router.clone(), surveys.clone(), dtmf.clone()));
+        Harness {
+            router,
+            surveys,
+            dtmf,
+            runner,
+            queue_id,
+        }
+    }
+
+    /// Answer a queue call by the agent and have them hang up
+    async fn agent_hangs_up(harness: &Harness) {
+        harness
+            .router
+            .create_call("call-1".to_string(), CALLER.to_string(), AGENT.to_string())
+            .await
+            .unwrap();
+        harness.router.answer_call("call-1").await.unwrap();
+        assert!(
+            harness
+                .runner
+                .offer("call-1", harness.queue_id, Some("agent1".to_string()))
+                .await
+        );
+        // A caller's BYE is not the agent's
+        assert!(!harness.runner.agent_left("call-1", CALLER).await);
+        assert!(harness.runner.agent_left("call-1", AGENT).await);
+    }
+
+    async fn press(harness: &Harness, digit: char) {
+        let event = DtmfEvent::new(DtmfDigit::from_char(digit).unwrap(), Duration::from_millis(100));
+        while harness.dtmf.publish("call-1", event.clone()) == 0 {
+            tokio::time::sleep(Duration::from_millis(5)).await;
+        }
+    }
+
+    async fn responses(harness: &Harness) -> Vec<SurveyResponse> {
+        for _ in 0..100 {
+            let responses = harness.surveys.responses(harness.queue_id);
+            if !responses.is_empty() {
+                return responses;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        panic!("No survey response stored");
+    }
+
+    #[tokio::test]
+    async fn test_completed_two_question_survey() {
+        let harness = harness();
+        agent_hangs_up(&harness).await;
+        // The caller is still connected after the agent's BYE
+        assert_eq!(
+            harness.router.get_active_call("call-1").await.unwrap().state,
+            "Established"
+        );
+
+        press(&harness, '4').await;
+        // Out of range for the second question: asked again
+        press(&harness, '7').await;
+        press(&harness, '1').await;
+
+        let responses = responses(&harness).await;
+        assert_eq!(responses.len(), 1);
+        let response = &responses[0];
+        assert_eq!(response.status, SurveyStatus::Completed);
+        assert_eq!(response.agent.as_deref(), Some("agent1"));
+        assert!(response.cdr_id.is_some());
+        let digits: Vec<u8> = response.answers.iter().map(|a| a.digit).collect();
+        assert_eq!(digits, vec![4, 1]);
+
+        // The PBX released the caller afterwards
+        for _ in 0..100 {
+            if harness.router.get_active_call("call-1").await.is_none() {
+                return;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        panic!("Call not released after the survey");
+    }
+
+    #[tokio::test]
+    async fn test_caller_hangup_mid_question_is_partial() {
+        let harness = harness();
+        agent_hangs_up(&harness).await;
+
+        press(&harness, '5').await;
+        // The caller hangs up during the second question
+        harness.runner.caller_left("call-1");
+        harness.router.terminate_call("call-1").await.unwrap();
+
+        let responses = responses(&harness).await;
+        assert_eq!(responses[0].status, SurveyStatus::Partial);
+        assert_eq!(responses[0].answers.len(), 1);
+        assert_eq!(harness.surveys.results(harness.queue_id).partial, 1);
+    }
+
+    #[tokio::test]
+    async fn test_caller_hangup_before_agent_skips_survey() {
+        let harness = harness();
+        harness
+            .router
+            .create_call("call-1".to_string(), CALLER.to_string(), AGENT.to_string())
+            .await
+            .unwrap();
+        harness.router.answer_call("call-1").await.unwrap();
+        assert!(harness.runner.offer("call-1", harness.queue_id, None).await);
+
+        harness.runner.caller_left("call-1");
+        harness.router.terminate_call("call-1").await.unwrap();
+        assert!(!harness.surveys.is_pending("call-1"));
+        assert!(!harness.runner.agent_left("call-1", AGENT).await);
+    }
+}
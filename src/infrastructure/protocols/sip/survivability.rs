@@ -0,0 +1,731 @@
+//! Survivability of branch deployments
+//!
+//! A branch node whose phones normally belong to a central system (the
+//! `upstream_realms`, reached over `upstream_trunk`) watches that trunk
+//! through an [`UpstreamMonitor`]. After `failure_threshold` failed checks
+//! in a row it enters survivability:
+//!
+//! - REGISTERs from the upstream realms, refused here while the central
+//!   system is up, are accepted with a short expiry, so phones go back
+//!   upstream soon after it returns
+//! - calls between extensions registered here are routed locally
+//! - emergency numbers and the `local_prefixes` go out the local gateway
+//!   trunk
+//! - every other outbound call is refused, with the announcement when one
+//!   is set
+//!
+//! CDRs of the calls made meanwhile and voicemail notification emails are
+//! queued, and handed to their [`UpstreamSync`] once the upstream answers
+//! again. The first successful check leaves survivability.
+
+use super::call_router::CallRouter;
+use super::outbound_registration::{OutboundRegistration, RegistrationState};
+use crate::domain::tenant_branding::realm_of;
+use crate::infrastructure::transcription::VoicemailEmailSender;
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use metrics::{counter, gauge};
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, VecDeque};
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::sync::broadcast;
+use tokio::task::JoinHandle;
+use tracing::{debug, info, warn};
+
+/// Seconds a caller refused in survivability hears the announcement
+/// before the call is ended
+pub const ANNOUNCEMENT_SECS: u64 = 10;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SurvivabilityConfig {
+    pub enabled: bool,
+    /// Trunk to the central system, by name, whose reachability is watched
+    pub upstream_trunk: String,
+    /// Realms of phones registered with the central system
+    pub upstream_realms: Vec<String>,
+    pub check_interval_secs: u64,
+    /// Failed checks in a row before survivability is entered
+    pub failure_threshold: u32,
+    /// Expiry granted to REGISTERs accepted in survivability
+    pub registration_expires_secs: u32,
+    /// Trunk, by name, to the branch's PSTN gateway
+    pub local_gateway_trunk: Option<String>,
+    /// Address of the PSTN gateway
+    pub local_gateway_address: Option<String>,
+    pub emergency_numbers: Vec<String>,
+    /// Prefixes of numbers sent out the local gateway
+    pub local_prefixes: Vec<String>,
+    /// Audio file played to callers of destinations only the central
+    /// system reaches, instead of a bare 503
+    pub announcement: Option<String>,
+    /// CDRs and notifications kept for the upstream; the oldest are
+    /// dropped beyond this
+    pub max_queued: usize,
+}
+
+impl Default for SurvivabilityConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            upstream_trunk: String::new(),
+            upstream_realms: Vec::new(),
+            check_interval_secs: 10,
+            failure_threshold: 3,
+            registration_expires_secs: 120,
+            local_gateway_trunk: None,
+            local_gateway_address: None,
+            emergency_numbers: vec!["112".to_string(), "911".to_string()],
+            local_prefixes: Vec::new(),
+            announcement: None,
+            max_queued: 10_000,
+        }
+    }
+}
+
+impl SurvivabilityConfig {
+    pub fn validate(&self) -> Result<(), String> {
+        if !self.enabled {
+            return Ok(());
+        }
+        if self.upstream_trunk.is_empty() {
+            return Err("upstream_trunk is required".to_string());
+        }
+        if self.check_interval_secs == 0 || self.failure_threshold == 0 {
+            return Err("check_interval_secs and failure_threshold must be at least 1".to_string());
+        }
+        if self.registration_expires_secs < 60 {
+            return Err(format!(
+                "registration_expires_secs {} is below 60",
+                self.registration_expires_secs
+            ));
+        }
+        match (&self.local_gateway_trunk, &self.local_gateway_address) {
+            (Some(_), Some(address)) => {
+                address
+                    .parse::<SocketAddr>()
+                    .map_err(|e| format!("local_gateway_address {}: {}", address, e))?;
+            }
+            (None, None) => {}
+            _ => {
+                return Err(
+                    "local_gateway_trunk and local_gateway_address go together".to_string(),
+                )
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Reachability of the central system
+#[async_trait]
+pub trait UpstreamMonitor: Send + Sync {
+    /// Whether the upstream answered its last keepalive
+    async fn upstream_reachable(&self) -> bool;
+}
+
+/// Upstream reachability from the registration of its trunk, whose
+/// refreshes serve as keepalive
+pub struct TrunkRegistrationMonitor {
+    registration: Arc<OutboundRegistration>,
+    trunk: String,
+}
+
+impl TrunkRegistrationMonitor {
+    pub fn new(registration: Arc<OutboundRegistration>, trunk: &str) -> Self {
+        Self {
+            registration,
+            trunk: trunk.to_string(),
+        }
+    }
+}
+
+#[async_trait]
+impl UpstreamMonitor for TrunkRegistrationMonitor {
+    async fn upstream_reachable(&self) -> bool {
+        let status = self
+            .registration
+            .statuses()
+            .into_iter()
+            .find(|status| status.trunk_name == self.trunk);
+        match status {
+            // A provider answering, even with a refusal, is reachable
+            Some(status) => !matches!(
+                status.state,
+                RegistrationState::Retrying { last_status: None, .. }
+            ),
+            None => {
+                debug!("Upstream trunk {} does not register", self.trunk);
+                true
+            }
+        }
+    }
+}
+
+/// Kind of a queued item
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DeferredKind {
+    Cdr,
+    VoicemailEmail,
+}
+
+/// Record kept for the upstream until it is reachable again
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum DeferredItem {
+    /// Call handled locally in survivability
+    Cdr { call_id: String },
+    VoicemailEmail {
+        to: String,
+        subject: String,
+        body: String,
+    },
+}
+
+impl DeferredItem {
+    pub fn kind(&self) -> DeferredKind {
+        match self {
+            DeferredItem::Cdr { .. } => DeferredKind::Cdr,
+            DeferredItem::VoicemailEmail { .. } => DeferredKind::VoicemailEmail,
+        }
+    }
+}
+
+/// Delivers queued items of one kind once the upstream is back
+#[async_trait]
+pub trait UpstreamSync: Send + Sync {
+    fn kind(&self) -> DeferredKind;
+
+    async fn deliver(&self, item: &DeferredItem) -> Result<(), String>;
+}
+
+/// Where a new call goes
+#[derive(Debug, Clone, PartialEq)]
+pub enum SurvivableRoute {
+    /// Not in survivability
+    Normal,
+    /// Between extensions registered here
+    Local,
+    /// Out the local gateway trunk
+    Gateway {
+        trunk: String,
+        target_uri: String,
+        address: SocketAddr,
+    },
+    /// Only the central system reaches the destination
+    Restricted { announcement: Option<String> },
+}
+
+/// How a REGISTER is answered
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SurvivableRegistration {
+    Normal,
+    /// The phone registers with the central system
+    Refused,
+    /// Accepted, for at most this many seconds
+    Accepted { expires: u32 },
+}
+
+/// Survivability state, as shown by the API
+#[derive(Debug, Clone, Serialize)]
+pub struct SurvivabilityStatus {
+    pub enabled: bool,
+    pub active: bool,
+    pub upstream_trunk: String,
+    pub since: Option<DateTime<Utc>>,
+    pub failed_checks: u32,
+    pub last_check: Option<DateTime<Utc>>,
+    pub local_calls: u64,
+    pub gateway_calls: u64,
+    pub restricted_calls: u64,
+    pub accepted_registrations: u64,
+    /// Items waiting for the upstream
+    pub queued: usize,
+}
+
+/// What happened to survivability
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SurvivabilityChange {
+    /// Upstream lost; routing is local
+    Entered,
+    /// Upstream back; routing is normal
+    Left,
+}
+
+/// Survivability change, published to subscribers
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SurvivabilityEvent {
+    pub change: SurvivabilityChange,
+    pub upstream_trunk: String,
+    /// Items handed to the upstream on leaving
+    pub synced: usize,
+    /// Items still waiting
+    pub queued: usize,
+    pub timestamp: DateTime<Utc>,
+}
+
+#[derive(Default)]
+struct State {
+    since: Option<DateTime<Utc>>,
+    failed_checks: u32,
+    last_check: Option<DateTime<Utc>>,
+    local_calls: u64,
+    gateway_calls: u64,
+    restricted_calls: u64,
+    accepted_registrations: u64,
+}
+
+/// Upstream reachability and the degraded routing profile
+pub struct SurvivabilityManager {
+    config: SurvivabilityConfig,
+    gateway: Option<SocketAddr>,
+    state: Mutex<State>,
+    queue: Mutex<VecDeque<DeferredItem>>,
+    syncs: HashMap<DeferredKind, Arc<dyn UpstreamSync>>,
+    events: broadcast::Sender<SurvivabilityEvent>,
+}
+
+impl SurvivabilityManager {
+    pub fn new(config: SurvivabilityConfig) -> Self {
+        let (events, _) = broadcast::channel(16);
+        let gateway = config
+            .local_gateway_address
+            .as_deref()
+            .and_then(|address| address.parse().ok());
+        Self {
+            config,
+            gateway,
+            state: Mutex::new(State::default()),
+            queue: Mutex::new(VecDeque::new()),
+            syncs: HashMap::new(),
+            events,
+        }
+    }
+
+    /// Deliver queued items of the sync's kind when the upstream is back
+    pub fn with_sync(mut self, sync: Arc<dyn UpstreamSync>) -> Self {
+        self.syncs.insert(sync.kind(), sync);
+        self
+    }
+
+    pub fn config(&self) -> &SurvivabilityConfig {
+        &self.config
+    }
+
+    /// Entering and leaving survivability
+    pub fn subscribe(&self) -> broadcast::Receiver<SurvivabilityEvent> {
+        self.events.subscribe()
+    }
+
+    pub fn is_active(&self) -> bool {
+        self.state.lock().unwrap().since.is_some()
+    }
+
+    pub fn status(&self) -> SurvivabilityStatus {
+        let queued = self.queue.lock().unwrap().len();
+        let state = self.state.lock().unwrap();
+        SurvivabilityStatus {
+            enabled: self.config.enabled,
+            active: state.since.is_some(),
+            upstream_trunk: self.config.upstream_trunk.clone(),
+            since: state.since,
+            failed_checks: state.failed_checks,
+            last_check: state.last_check,
+            local_calls: state.local_calls,
+            gateway_calls: state.gateway_calls,
+            restricted_calls: state.restricted_calls,
+            accepted_registrations: state.accepted_registrations,
+            queued,
+        }
+    }
+
+    /// Record a reachability check; enters survivability after
+    /// `failure_threshold` failures in a row, and leaves it, handing the
+    /// queue to the upstream, on the first success
+    pub async fn observe(&self, reachable: bool) -> Option<SurvivabilityChange> {
+        let change = {
+            let mut state = self.state.lock().unwrap();
+            state.last_check = Some(Utc::now());
+            if reachable {
+                state.failed_checks = 0;
+                state.since.take().map(|_| SurvivabilityChange::Left)
+            } else {
+                state.failed_checks = state.failed_checks.saturating_add(1);
+                if state.since.is_none() && state.failed_checks >= self.config.failure_threshold {
+                    state.since = Some(Utc::now());
+                    Some(SurvivabilityChange::Entered)
+                } else {
+                    None
+                }
+            }
+        }?;
+
+        let synced = match change {
+            SurvivabilityChange::Entered => {
+                warn!(
+                    "Upstream trunk {} unreachable: survivability entered",
+                    self.config.upstream_trunk
+                );
+                0
+            }
+            SurvivabilityChange::Left => {
+                let synced = self.flush().await;
+                info!(
+                    "Upstream trunk {} reachable: survivability left, {} items synced",
+                    self.config.upstream_trunk, synced
+                );
+                synced
+            }
+        };
+        gauge!("survivability_active").set(if change == SurvivabilityChange::Entered { 1.0 } else { 0.0 });
+        counter!("survivability_transitions_total", "change" => match change {
+            SurvivabilityChange::Entered => "entered",
+            SurvivabilityChange::Left => "left",
+        })
+        .increment(1);
+        // No subscribers is fine
+        let _ = self.events.send(SurvivabilityEvent {
+            change,
+            upstream_trunk: self.config.upstream_trunk.clone(),
+            synced,
+            queued: self.queue.lock().unwrap().len(),
+            timestamp: Utc::now(),
+        });
+        Some(change)
+    }
+
+    /// Check the upstream every `check_interval_secs`
+    pub fn spawn(self: Arc<Self>, monitor: Arc<dyn UpstreamMonitor>) -> JoinHandle<()> {
+        tokio::spawn(async move {
+            let mut ticker =
+                tokio::time::interval(Duration::from_secs(self.config.check_interval_secs));
+            loop {
+                ticker.tick().await;
+                let reachable = monitor.upstream_reachable().await;
+                self.observe(reachable).await;
+            }
+        })
+    }
+
+    /// Route of a new call to `callee_uri`; `registered` tells whether the
+    /// callee is registered here. Counted by route.
+    pub fn route(&self, callee_uri: &str, registered: bool) -> SurvivableRoute {
+        let mut state = self.state.lock().unwrap();
+        if state.since.is_none() {
+            return SurvivableRoute::Normal;
+        }
+        if registered {
+            state.local_calls += 1;
+            return SurvivableRoute::Local;
+        }
+
+        let number = CallRouter::extract_username(callee_uri);
+        let via_gateway = self.config.emergency_numbers.contains(&number)
+            || self
+                .config
+                .local_prefixes
+                .iter()
+                .any(|prefix| number.starts_with(prefix.as_str()));
+        if let (true, Some(trunk), Some(address)) =
+            (via_gateway, &self.config.local_gateway_trunk, self.gateway)
+        {
+            state.gateway_calls += 1;
+            return SurvivableRoute::Gateway {
+                trunk: trunk.clone(),
+                target_uri: format!("sip:{}@{}", number, address),
+                address,
+            };
+        }
+
+        state.restricted_calls += 1;
+        counter!("survivability_restricted_calls_total").increment(1);
+        SurvivableRoute::Restricted {
+            announcement: self.config.announcement.clone(),
+        }
+    }
+
+    /// How a REGISTER for `aor` is answered
+    pub fn registration(&self, aor: &str) -> SurvivableRegistration {
+        let upstream = realm_of(aor).is_some_and(|realm| {
+            self.config
+                .upstream_realms
+                .iter()
+                .any(|upstream| upstream.eq_ignore_ascii_case(realm))
+        });
+        if !upstream {
+            return SurvivableRegistration::Normal;
+        }
+        let mut state = self.state.lock().unwrap();
+        if state.since.is_none() {
+            return SurvivableRegistration::Refused;
+        }
+        state.accepted_registrations += 1;
+        SurvivableRegistration::Accepted {
+            expires: self.config.registration_expires_secs,
+        }
+    }
+
+    /// Keep `item` for the upstream
+    pub fn defer(&self, item: DeferredItem) {
+        let mut queue = self.queue.lock().unwrap();
+        if queue.len() >= self.config.max_queued {
+            warn!("Survivability queue full, dropping the oldest item");
+            queue.pop_front();
+        }
+        queue.push_back(item);
+        gauge!("survivability_queued").set(queue.len() as f64);
+    }
+
+    /// Hand queued items to their syncs; returns how many were delivered
+    ///
+    /// Items whose delivery fails stay queued for the next recovery; items
+    /// without a sync are dropped.
+    pub async fn flush(&self) -> usize {
+        let items: Vec<DeferredItem> = self.queue.lock().unwrap().drain(..).collect();
+        let mut failed = Vec::new();
+        let mut synced = 0;
+        for item in items {
+            let Some(sync) = self.syncs.get(&item.kind()) else {
+                debug!("No upstream sync for {:?}, dropped", item.kind());
+                continue;
+            };
+            match sync.deliver(&item).await {
+                Ok(()) => synced += 1,
+                Err(e) => {
+                    warn!("Failed to sync {:?} upstream: {}", item.kind(), e);
+                    failed.push(item);
+                }
+            }
+        }
+        let mut queue = self.queue.lock().unwrap();
+        for item in failed.into_iter().rev() {
+            queue.push_front(item);
+        }
+        gauge!("survivability_queued").set(queue.len() as f64);
+        synced
+    }
+}
+
+/// Voicemail email sender that holds notifications while in survivability
+pub struct SurvivableEmailSender {
+    inner: Arc<dyn VoicemailEmailSender>,
+    survivability: Arc<SurvivabilityManager>,
+}
+
+impl SurvivableEmailSender {
+    pub fn new(
+        inner: Arc<dyn VoicemailEmailSender>,
+        survivability: Arc<SurvivabilityManager>,
+    ) -> Self {
+        Self {
+            inner,
+            survivability,
+        }
+    }
+}
+
+#[async_trait]
+impl VoicemailEmailSender for SurvivableEmailSender {
+    async fn send(&self, to: &str, subject: &str, body: &str) -> Result<(), String> {
+        if !self.survivability.is_active() {
+            return self.inner.send(to, subject, body).await;
+        }
+        debug!("Voicemail email to {} held for the upstream", to);
+        self.survivability.defer(DeferredItem::VoicemailEmail {
+            to: to.to_string(),
+            subject: subject.to_string(),
+            body: body.to_string(),
+        });
+        Ok(())
+    }
+}
+
+/// Delivers held voicemail emails through the sender they were held from
+pub struct VoicemailEmailSync(pub Arc<dyn VoicemailEmailSender>);
+
+#[async_trait]
+impl UpstreamSync for VoicemailEmailSync {
+    fn kind(&self) -> DeferredKind {
+        DeferredKind::VoicemailEmail
+    }
+
+    async fn deliver(&self, item: &DeferredItem) -> Result<(), String> {
+        match item {
+            DeferredItem::VoicemailEmail { to, subject, body } => {
+                self.0.send(to, subject, body).await
+            }
+            other => Err(format!("Not a voicemail email: {:?}", other.kind())),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{header_value, TestServer};
+    use std::sync::atomic::{AtomicBool, Ordering};
+
+    /// Trunk keepalive answered or not, as the test says
+    struct MockTrunkMonitor(AtomicBool);
+
+    #[async_trait]
+    impl UpstreamMonitor for MockTrunkMonitor {
+        async fn upstream_reachable(&self) -> bool {
+            self.0.load(Ordering::SeqCst)
+        }
+    }
+
+    struct RecordingEmail {
+        sent: Mutex<Vec<String>>,
+        fail: AtomicBool,
+    }
+
+    #[async_trait]
+    impl VoicemailEmailSender for RecordingEmail {
+        async fn send(&self, to: &str, _subject: &str, _body: &str) -> Result<(), String> {
+            if self.fail.load(Ordering::SeqCst) {
+                return Err("relay unreachable".to_string());
+            }
+            self.sent.lock().unwrap().push(to.to_string());
+            Ok(())
+        }
+    }
+
+    fn config() -> SurvivabilityConfig {
+        SurvivabilityConfig {
+            enabled: true,
+            upstream_trunk: "hq".to_string(),
+            upstream_realms: vec!["branch.test".to_string()],
+            failure_threshold: 2,
+            local_gateway_trunk: Some("pstn".to_string()),
+            local_gateway_address: Some("127.0.0.1:5099".to_string()),
+            local_prefixes: vec!["0".to_string()],
+            announcement: Some("upstream-unavailable".to_string()),
+            ..Default::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn test_upstream_loss_keeps_local_calls_and_restricts_the_rest() {
+        let survivability = Arc::new(SurvivabilityManager::new(config()));
+        let mut events = survivability.subscribe();
+        let monitor = MockTrunkMonitor(AtomicBool::new(true));
+        let server = TestServer::builder()
+            .domain("branch.test")
+            .survivability(survivability.clone())
+            .start()
+            .await
+            .unwrap();
+        let alice = server.ua("alice").await;
+        let bob = server.ua("bob").await;
+
+        // Phones register with the central system while it is up
+        assert_eq!(bob.register(server.addr()).await.status_code(), 503);
+        assert_eq!(survivability.observe(monitor.upstream_reachable().await).await, None);
+
+        // Upstream lost: entered once the threshold is reached
+        monitor.0.store(false, Ordering::SeqCst);
+        assert_eq!(survivability.observe(monitor.upstream_reachable().await).await, None);
+        assert_eq!(
+            survivability.observe(monitor.upstream_reachable().await).await,
+            Some(SurvivabilityChange::Entered)
+        );
+        assert_eq!(events.recv().await.unwrap().change, SurvivabilityChange::Entered);
+        assert!(survivability.status().active);
+
+        let register = bob.register(server.addr()).await;
+        assert_eq!(register.status_code(), 200);
+        assert_eq!(header_value(register.headers(), "Expires").as_deref(), Some("120"));
+
+        // Extension to extension completes locally
+        let local = alice.invite(server.addr(), "sip:bob@branch.test").await;
+        assert_eq!(local.last().unwrap().status_code(), 200);
+
+        // Emergency calls go out the local gateway
+        let emergency = alice.invite(server.addr(), "sip:112@branch.test").await;
+        let emergency = emergency.last().unwrap();
+        assert_eq!(emergency.status_code(), 200);
+        assert_eq!(
+            server
+                .call_router()
+                .get_callee_contact(&emergency.call_id().unwrap())
+                .await,
+            "127.0.0.1:5099".parse().ok()
+        );
+
+        // A destination only the central system reaches gets the
+        // announcement instead of ringing
+        let restricted = alice.invite(server.addr(), "sip:+4930555@branch.test").await;
+        let restricted = restricted.last().unwrap();
+        assert_eq!(restricted.status_code(), 200);
+        assert!(server
+            .call_router()
+            .get_callee_contact(&restricted.call_id().unwrap())
+            .await
+            .is_none());
+        let status = survivability.status();
+        assert_eq!(
+            (status.local_calls, status.gateway_calls, status.restricted_calls),
+            (1, 1, 1)
+        );
+        assert_eq!(status.accepted_registrations, 1);
+        assert_eq!(status.queued, 2);
+
+        // Upstream back: normal routing, registrations go upstream again
+        monitor.0.store(true, Ordering::SeqCst);
+        assert_eq!(
+            survivability.observe(monitor.upstream_reachable().await).await,
+            Some(SurvivabilityChange::Left)
+        );
+        let left = events.recv().await.unwrap();
+        assert_eq!((left.change, left.queued), (SurvivabilityChange::Left, 0));
+        assert!(!survivability.status().active);
+        assert_eq!(bob.register(server.addr()).await.status_code(), 503);
+        let after = alice.invite(server.addr(), "sip:+4930555@branch.test").await;
+        assert_eq!(after.last().unwrap().status_code(), 404);
+
+        server.stop().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_voicemail_emails_are_held_until_the_upstream_is_back() {
+        let relay = Arc::new(RecordingEmail {
+            sent: Mutex::new(Vec::new()),
+            fail: AtomicBool::new(false),
+        });
+        let survivability = Arc::new(
+            SurvivabilityManager::new(SurvivabilityConfig {
+                failure_threshold: 1,
+                ..config()
+            })
+            .with_sync(Arc::new(VoicemailEmailSync(relay.clone()))),
+        );
+        let email = SurvivableEmailSender::new(relay.clone(), survivability.clone());
+
+        email.send("alice@example.com", "New voicemail", "").await.unwrap();
+        assert_eq!(relay.sent.lock().unwrap().len(), 1);
+
+        survivability.observe(false).await;
+        email.send("bob@example.com", "New voicemail", "").await.unwrap();
+        assert_eq!(relay.sent.lock().unwrap().len(), 1);
+        assert_eq!(survivability.status().queued, 1);
+
+        // Failed deliveries wait for the next recovery
+        relay.fail.store(true, Ordering::SeqCst);
+        survivability.observe(true).await;
+        assert_eq!(survivability.status().queued, 1);
+
+        relay.fail.store(false, Ordering::SeqCst);
+        survivability.observe(false).await;
+        survivability.observe(true).await;
+        assert_eq!(survivability.status().queued, 0);
+        assert_eq!(
+            *relay.sent.lock().unwrap(),
+            vec!["alice@example.com".to_string(), "bob@example.com".to_string()]
+        );
+    }
+}
@@ -9,7 +9,10 @@
 //! - Non-INVITE Server Transaction (NIST) - Section 17.2.2
 
 use super::message::{SipRequest, SipResponse};
+use super::transport::TransportProtocol;
+use crate::infrastructure::clock::{system_clock, Clock};
 use rsip::{Header, Headers};
+use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
 use std::net::SocketAddr;
 use std::sync::Arc;
@@ -20,7 +23,7 @@ use tokio::time::Instant;
 use tracing::{debug, info, warn};
 
 /// Extract branch parameter from Via header
-fn extract_branch(headers: &Headers) -> Option<String> {
+pub(super) fn extract_branch(headers: &Headers) -> Option<String> {
     headers.iter().find_map(|h| match h {
         Header::Via(via) => {
             // Convert Via header to string and extract branch parameter
@@ -47,16 +50,20 @@ impl TransactionId {
     }
 
     /// Generate a new transaction ID
+    ///
+    /// 128 bits from the thread-local CSPRNG, so branches are unique across
+    /// requests and servers. Generate once per request; retransmissions
+    /// must reuse the branch.
     pub fn generate() -> Self {
         use rand::Rng;
         let mut rng = rand::thread_rng();
-        let random: u64 = rng.gen();
-        Self(format!("z9hG4bK{:x}", random))
+        let random: u128 = rng.gen();
+        Self(format!("z9hG4bK{:032x}", random))
     }
 }
 
 /// SIP Timers (RFC 3261 Section 17.1.1.1)
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub struct SipTimers {
     /// T1: RTT Estimate (default 500ms)
     pub t1: Duration,
@@ -64,6 +71,12 @@ pub struct SipTimers {
     pub t2: Duration,
     /// T4: Maximum duration a message remains in network (default 5s)
     pub t4: Duration,
+    /// Timer B (INVITE transaction timeout) in multiples of T1 (default 64)
+    pub timer_b_factor: u32,
+    /// Timer F (non-INVITE transaction timeout) in multiples of T1 (default 64)
+    pub timer_f_factor: u32,
+    /// Timer D: wait for response retransmits over UDP (default 32s)
+    pub timer_d: Duration,
 }
 
 impl Default for SipTimers {
@@ -72,6 +85,227 @@ impl Default for SipTimers {
             t1: Duration::from_millis(500),
             t2: Duration::from_secs(4),
             t4: Duration::from_secs(5),
+            timer_b_factor: 64,
+            timer_f_factor: 64,
+            timer_d: Duration::from_secs(32),
+        }
+    }
+}
+
+impl SipTimers {
+    pub fn validate(&self) -> Result<(), String> {
+        if self.t1.is_zero() {
+            return Err("t1 must be greater than zero".to_string());
+        }
+        if self.t2 < self.t1 {
+            return Err(format!(
+                "t2 ({}ms) must not be shorter than t1 ({}ms)",
+                self.t2.as_millis(),
+                self.t1.as_millis()
+            ));
+        }
+        if self.t4.is_zero() {
+            return Err("t4 must be greater than zero".to_string());
+        }
+        if self.timer_b_factor == 0 || self.timer_f_factor == 0 {
+            return Err("timer_b_factor and timer_f_factor must be at least 1".to_string());
+        }
+        if self.timer_d.is_zero() {
+            return Err("timer_d must be greater than zero".to_string());
+        }
+        Ok(())
+    }
+}
+
+/// Timer values that replace the ones they are layered on; unset values
+/// are inherited
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SipTimerOverrides {
+    pub t1_ms: Option<u64>,
+    pub t2_ms: Option<u64>,
+    pub t4_ms: Option<u64>,
+    pub timer_b_factor: Option<u32>,
+    pub timer_f_factor: Option<u32>,
+    pub timer_d_ms: Option<u64>,
+}
+
+impl SipTimerOverrides {
+    fn apply(&self, mut timers: SipTimers) -> SipTimers {
+        if let Some(ms) = self.t1_ms {
+            timers.t1 = Duration::from_millis(ms);
+        }
+        if let Some(ms) = self.t2_ms {
+            timers.t2 = Duration::from_millis(ms);
+        }
+        if let Some(ms) = self.t4_ms {
+            timers.t4 = Duration::from_millis(ms);
+        }
+        if let Some(factor) = self.timer_b_factor {
+            timers.timer_b_factor = factor;
+        }
+        if let Some(factor) = self.timer_f_factor {
+            timers.timer_f_factor = factor;
+        }
+        if let Some(ms) = self.timer_d_ms {
+            timers.timer_d = Duration::from_millis(ms);
+        }
+        timers
+    }
+}
+
+/// Transaction timers (`[sip.timers]`)
+///
+/// The base values apply to every transaction; a transport's overrides are
+/// layered on them, and a trunk's overrides on top of those.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SipTimerConfig {
+    pub t1_ms: u64,
+    pub t2_ms: u64,
+    pub t4_ms: u64,
+    /// Timer B in multiples of T1
+    pub timer_b_factor: u32,
+    /// Timer F in multiples of T1
+    pub timer_f_factor: u32,
+    pub timer_d_ms: u64,
+    pub udp: SipTimerOverrides,
+    pub tcp: SipTimerOverrides,
+    pub tls: SipTimerOverrides,
+    /// Overrides for trunks known to be slow, by trunk name
+    pub trunks: HashMap<String, SipTimerOverrides>,
+}
+
+impl Default for SipTimerConfig {
+    fn default() -> Self {
+        let timers = SipTimers::default();
+        Self {
+            t1_ms: timers.t1.as_millis() as u64,
+            t2_ms: timers.t2.as_millis() as u64,
+            t4_ms: timers.t4.as_millis() as u64,
+            timer_b_factor: timers.timer_b_factor,
+            timer_f_factor: timers.timer_f_factor,
+            timer_d_ms: timers.timer_d.as_millis() as u64,
+            udp: SipTimerOverrides::default(),
+            tcp: SipTimerOverrides::default(),
+            tls: SipTimerOverrides::default(),
+            trunks: HashMap::new(),
+        }
+    }
+}
+
+/// Transports with their own timer overrides
+const TIMER_TRANSPORTS: [TransportProtocol; 3] =
+    [TransportProtocol::Udp, TransportProtocol::Tcp, TransportProtocol::Tls];
+
+impl SipTimerConfig {
+    fn base(&self) -> SipTimers {
+        SipTimers {
+            t1: Duration::from_millis(self.t1_ms),
+            t2: Duration::from_millis(self.t2_ms),
+            t4: Duration::from_millis(self.t4_ms),
+            timer_b_factor: self.timer_b_factor,
+            timer_f_factor: self.timer_f_factor,
+            timer_d: Duration::from_millis(self.timer_d_ms),
+        }
+    }
+
+    fn transport_overrides(&self, transport: TransportProtocol) -> Option<&SipTimerOverrides> {
+        match transport {
+            TransportProtocol::Udp => Some(&self.udp),
+            TransportProtocol::Tcp => Some(&self.tcp),
+            TransportProtocol::Tls => Some(&self.tls),
+            TransportProtocol::Ws | TransportProtocol::Wss => None,
+        }
+    }
+
+    /// Timers of a transaction over `transport`, towards `trunk` if any
+    pub fn timers_for(&self, transport: TransportProtocol, trunk: Option<&str>) -> SipTimers {
+        let mut timers = self.base();
+        if let Some(overrides) = self.transport_overrides(transport) {
+            timers = overrides.apply(timers);
+        }
+        if let Some(overrides) = trunk.and_then(|name| self.trunks.get(name)) {
+            timers = overrides.apply(timers);
+        }
+        timers
+    }
+
+    /// Check every combination of transport and trunk
+    pub fn validate(&self) -> Result<(), String> {
+        self.base().validate()?;
+        for transport in TIMER_TRANSPORTS {
+            self.timers_for(transport, None)
+                .validate()
+                .map_err(|e| format!("{}: {}", transport.as_str().to_lowercase(), e))?;
+            for name in self.trunks.keys() {
+                self.timers_for(transport, Some(name))
+                    .validate()
+                    .map_err(|e| {
+                        format!("trunks.{} over {}: {}", name, transport.as_str(), e)
+                    })?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Effective timer values per transport and per trunk, for diagnostics
+    pub fn effective(&self) -> Vec<EffectiveSipTimers> {
+        let mut trunks: Vec<&String> = self.trunks.keys().collect();
+        trunks.sort();
+        let mut effective = Vec::new();
+        for transport in TIMER_TRANSPORTS {
+            effective.push(EffectiveSipTimers::new(
+                None,
+                transport,
+                self.timers_for(transport, None),
+            ));
+            for name in &trunks {
+                effective.push(EffectiveSipTimers::new(
+                    Some(name.to_string()),
+                    transport,
+                    self.timers_for(transport, Some(name)),
+                ));
+            }
+        }
+        effective
+    }
+}
+
+/// Timer values a transaction gets, in milliseconds
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct EffectiveSipTimers {
+    pub trunk: Option<String>,
+    pub transport: TransportProtocol,
+    pub t1_ms: u64,
+    pub t2_ms: u64,
+    pub t4_ms: u64,
+    pub timer_b_ms: u64,
+    pub timer_d_ms: u64,
+    pub timer_f_ms: u64,
+    pub timer_h_ms: u64,
+    pub timer_i_ms: u64,
+    pub timer_j_ms: u64,
+    pub timer_k_ms: u64,
+}
+
+impl EffectiveSipTimers {
+    fn new(trunk: Option<String>, transport: TransportProtocol, timers: SipTimers) -> Self {
+        let reliable = transport != TransportProtocol::Udp;
+        let ms = |timer: TimerType| timer.default_duration(&timers, reliable).as_millis() as u64;
+        Self {
+            trunk,
+            transport,
+            t1_ms: timers.t1.as_millis() as u64,
+            t2_ms: timers.t2.as_millis() as u64,
+            t4_ms: timers.t4.as_millis() as u64,
+            timer_b_ms: ms(TimerType::TimerB),
+            timer_d_ms: ms(TimerType::TimerD),
+            timer_f_ms: ms(TimerType::TimerF),
+            timer_h_ms: ms(TimerType::TimerH),
+            timer_i_ms: ms(TimerType::TimerI),
+            timer_j_ms: ms(TimerType::TimerJ),
+            timer_k_ms: ms(TimerType::TimerK),
         }
     }
 }
@@ -81,13 +315,13 @@ impl Default for SipTimers {
 pub enum TimerType {
     /// Timer A: INVITE request retransmit interval (default: T1)
     TimerA,
-    /// Timer B: INVITE transaction timeout (default: 64*T1)
+    /// Timer B: INVITE transaction timeout (default: 64*T1, see `timer_b_factor`)
     TimerB,
     /// Timer D: Wait time for response retransmits (default: >32s for UDP, 0s for TCP)
     TimerD,
     /// Timer E: Non-INVITE request retransmit (default: T1)
     TimerE,
-    /// Timer F: Non-INVITE transaction timeout (default: 64*T1)
+    /// Timer F: Non-INVITE transaction timeout (default: 64*T1, see `timer_f_factor`)
     TimerF,
     /// Timer G: INVITE response retransmit (default: T1)
     TimerG,
@@ -106,16 +340,16 @@ impl TimerType {
     pub fn default_duration(&self, timers: &SipTimers, is_reliable: bool) -> Duration {
         match self {
             TimerType::TimerA => timers.t1,
-            TimerType::TimerB => timers.t1 * 64,
+            TimerType::TimerB => timers.t1 * timers.timer_b_factor,
             TimerType::TimerD => {
                 if is_reliable {
                     Duration::from_secs(0)
                 } else {
-                    Duration::from_secs(32)
+                    timers.timer_d
                 }
             }
             TimerType::TimerE => timers.t1,
-            TimerType::TimerF => timers.t1 * 64,
+            TimerType::TimerF => timers.t1 * timers.timer_f_factor,
             TimerType::TimerG => timers.t1,
             TimerType::TimerH => timers.t1 * 64,
             TimerType::TimerI => {
@@ -279,6 +513,8 @@ pub struct Transaction {
     pub created_at: Instant,
     /// SIP timer configuration
     pub sip_timers: SipTimers,
+    /// Time source of the timers
+    clock: Arc<dyn Clock>,
 }
 
 impl Transaction {
@@ -300,6 +536,7 @@ impl Transaction {
             last_response: None,
             created_at: Instant::now(),
             sip_timers,
+            clock: system_clock(),
         };
 
         // Start Timer A (request retransmit) and Timer B (timeout)
@@ -328,6 +565,7 @@ impl Transaction {
             last_response: None,
             created_at: Instant::now(),
             sip_timers: SipTimers::default(),
+            clock: system_clock(),
         }
     }
 
@@ -349,6 +587,7 @@ impl Transaction {
             last_response: None,
             created_at: Instant::now(),
             sip_timers,
+            clock: system_clock(),
         };
 
         // Start Timer E (request retransmit) and Timer F (timeout)
@@ -377,7 +616,34 @@ impl Transaction {
             last_response: None,
             created_at: Instant::now(),
             sip_timers: SipTimers::default(),
+            clock: system_clock(),
+        }
+    }
+
+    /// Run the transaction's timers on `clock`
+    ///
+    /// Timers already started are rescheduled from the clock's current time.
+    pub fn with_clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        let now = clock.instant();
+        for timer in &mut self.timers {
+            timer.expires_at = now + timer.interval;
+        }
+        self.created_at = now;
+        self.clock = clock;
+        self
+    }
+
+    /// Timer values of the transaction (RFC defaults unless set)
+    ///
+    /// Timers already started are restarted with the new durations.
+    pub fn with_timers(mut self, timers: SipTimers) -> Self {
+        self.sip_timers = timers;
+        let started: Vec<TimerType> = self.timers.iter().map(|t| t.timer_type).collect();
+        self.timers.clear();
+        for timer_type in started {
+            self.start_timer(timer_type);
         }
+        self
     }
 
     /// Start a timer
@@ -386,7 +652,7 @@ impl Transaction {
         if duration.as_millis() > 0 {
             let timer = ActiveTimer {
                 timer_type,
-                expires_at: Instant::now() + duration,
+                expires_at: self.clock.instant() + duration,
                 interval: duration,
             };
             self.timers.push(timer);
@@ -539,6 +805,7 @@ impl Transaction {
 
     /// Handle timer expiration
     pub fn handle_timer_fired(&mut self, timer_type: TimerType) -> TransactionTimerAction {
+        let now = self.clock.instant();
         debug!(
             "Timer {:?} fired for transaction {} in state {}",
             timer_type,
@@ -553,7 +820,7 @@ impl Transaction {
                     // Double the interval (exponential backoff)
                     if let Some(timer) = self.timers.iter_mut().find(|t| t.timer_type == TimerType::TimerA) {
                         timer.interval = std::cmp::min(timer.interval * 2, self.sip_timers.t2);
-                        timer.expires_at = Instant::now() + timer.interval;
+                        timer.expires_at = now + timer.interval;
                     }
                     TransactionTimerAction::RetransmitRequest
                 } else {
@@ -582,7 +849,7 @@ impl Transaction {
                     // Double the interval (exponential backoff)
                     if let Some(timer) = self.timers.iter_mut().find(|t| t.timer_type == TimerType::TimerE) {
                         timer.interval = std::cmp::min(timer.interval * 2, self.sip_timers.t2);
-                        timer.expires_at = Instant::now() + timer.interval;
+                        timer.expires_at = now + timer.interval;
                     }
                     TransactionTimerAction::RetransmitRequest
                 } else {
@@ -603,7 +870,7 @@ impl Transaction {
                     // Double the interval
                     if let Some(timer) = self.timers.iter_mut().find(|t| t.timer_type == TimerType::TimerG) {
                         timer.interval = std::cmp::min(timer.interval * 2, self.sip_timers.t2);
-                        timer.expires_at = Instant::now() + timer.interval;
+                        timer.expires_at = now + timer.interval;
                     }
                     TransactionTimerAction::RetransmitResponse
                 } else {
@@ -643,7 +910,7 @@ impl Transaction {
 
     /// Check for expired timers and return actions
     pub fn check_timers(&mut self) -> Vec<(TimerType, TransactionTimerAction)> {
-        let now = Instant::now();
+        let now = self.clock.instant();
         let mut actions = Vec::new();
 
         // Find expired timers
@@ -685,10 +952,13 @@ pub enum TransactionTimerAction {
 pub struct TransactionLayer {
     /// Active transactions indexed by transaction ID
     transactions: Arc<RwLock<HashMap<TransactionId, Transaction>>>,
-    /// SIP timer configuration
-    sip_timers: SipTimers,
+    /// Timer configuration; read when a transaction is created, so a
+    /// reload only affects new transactions
+    timer_config: std::sync::RwLock<Arc<SipTimerConfig>>,
     /// Background timer task handle
     timer_task: Option<JoinHandle<()>>,
+    /// Time source of the transactions' timers
+    clock: Arc<dyn Clock>,
 }
 
 impl TransactionLayer {
@@ -696,11 +966,39 @@ impl TransactionLayer {
     pub fn new() -> Self {
         Self {
             transactions: Arc::new(RwLock::new(HashMap::new())),
-            sip_timers: SipTimers::default(),
+            timer_config: std::sync::RwLock::new(Arc::new(SipTimerConfig::default())),
             timer_task: None,
+            clock: system_clock(),
         }
     }
 
+    /// Time source of the transactions' timers (the system clock by default)
+    pub fn with_clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    /// Timer configuration of new transactions (RFC defaults by default)
+    pub fn with_timer_config(self, config: SipTimerConfig) -> Self {
+        *self.timer_config.write().unwrap() = Arc::new(config);
+        self
+    }
+
+    /// Replace the timer configuration
+    ///
+    /// Transactions already running keep the timers they were created with.
+    pub fn reload_timer_config(&self, config: SipTimerConfig) -> Result<(), String> {
+        config.validate()?;
+        *self.timer_config.write().unwrap() = Arc::new(config);
+        info!("SIP timer configuration reloaded");
+        Ok(())
+    }
+
+    /// Current timer configuration
+    pub fn timer_config(&self) -> Arc<SipTimerConfig> {
+        self.timer_config.read().unwrap().clone()
+    }
+
     /// Start the transaction layer timer processing
     /// Returns a handle to the background timer task
     pub fn start(&mut self) -> &JoinHandle<()> {
@@ -711,60 +1009,72 @@ impl TransactionLayer {
 
             loop {
                 tokio::time::sleep(Duration::from_millis(50)).await;
+                Self::process_timers_of(&transactions).await;
+            }
+        });
 
-                // Process timers for all transactions
-                let actions = {
-                    let mut txns = transactions.write().await;
-                    let mut all_actions = Vec::new();
+        self.timer_task = Some(handle);
+        self.timer_task.as_ref().unwrap()
+    }
 
-                    for (id, txn) in txns.iter_mut() {
-                        let timer_actions = txn.check_timers();
-                        for (timer_type, action) in timer_actions {
-                            all_actions.push((id.clone(), timer_type, action));
-                        }
-                    }
+    /// Fire the expired timers of every transaction and drop the
+    /// terminated ones
+    ///
+    /// The timer task does this every 50ms; tests driving a manual clock can
+    /// call it right after advancing the clock.
+    pub async fn process_timers(&self) {
+        Self::process_timers_of(&self.transactions).await;
+    }
 
-                    all_actions
-                };
+    async fn process_timers_of(transactions: &RwLock<HashMap<TransactionId, Transaction>>) {
+        // Process timers for all transactions
+        let actions = {
+            let mut txns = transactions.write().await;
+            let mut all_actions = Vec::new();
 
-                // Log timer actions
-                for (id, timer_type, action) in actions {
-                    match action {
-                        TransactionTimerAction::RetransmitRequest => {
-                            debug!("Transaction {} timer {:?} fired: retransmit request", id.0, timer_type);
-                        }
-                        TransactionTimerAction::RetransmitResponse => {
-                            debug!("Transaction {} timer {:?} fired: retransmit response", id.0, timer_type);
-                        }
-                        TransactionTimerAction::Timeout => {
-                            warn!("Transaction {} timer {:?} fired: timeout", id.0, timer_type);
-                        }
-                        TransactionTimerAction::Terminate => {
-                            debug!("Transaction {} timer {:?} fired: terminate", id.0, timer_type);
-                        }
-                        TransactionTimerAction::None => {}
-                    }
+            for (id, txn) in txns.iter_mut() {
+                let timer_actions = txn.check_timers();
+                for (timer_type, action) in timer_actions {
+                    all_actions.push((id.clone(), timer_type, action));
                 }
+            }
 
-                // Cleanup terminated transactions
-                {
-                    let mut txns = transactions.write().await;
-                    let terminated: Vec<TransactionId> = txns
-                        .iter()
-                        .filter(|(_, txn)| txn.state.is_terminated())
-                        .map(|(id, _)| id.clone())
-                        .collect();
-
-                    for id in terminated {
-                        debug!("Removing terminated transaction {}", id.0);
-                        txns.remove(&id);
-                    }
+            all_actions
+        };
+
+        // Log timer actions
+        for (id, timer_type, action) in actions {
+            match action {
+                TransactionTimerAction::RetransmitRequest => {
+                    debug!("Transaction {} timer {:?} fired: retransmit request", id.0, timer_type);
+                }
+                TransactionTimerAction::RetransmitResponse => {
+                    debug!("Transaction {} timer {:?} fired: retransmit response", id.0, timer_type);
+                }
+                TransactionTimerAction::Timeout => {
+                    warn!("Transaction {} timer {:?} fired: timeout", id.0, timer_type);
+                }
+                TransactionTimerAction::Terminate => {
+                    debug!("Transaction {} timer {:?} fired: terminate", id.0, timer_type);
                 }
+                TransactionTimerAction::None => {}
             }
-        });
+        }
 
-        self.timer_task = Some(handle);
-        self.timer_task.as_ref().unwrap()
+        // Cleanup terminated transactions
+        {
+            let mut txns = transactions.write().await;
+            let terminated: Vec<TransactionId> = txns
+                .iter()
+                .filter(|(_, txn)| txn.state.is_terminated())
+                .map(|(id, _)| id.clone())
+                .collect();
+
+            for id in terminated {
+                debug!("Removing terminated transaction {}", id.0);
+                txns.remove(&id);
+            }
+        }
     }
 
     /// Stop the transaction layer
@@ -782,6 +1092,22 @@ impl TransactionLayer {
         destination: SocketAddr,
         is_reliable: bool,
     ) -> Result<TransactionId, String> {
+        let transport = if is_reliable { TransportProtocol::Tcp } else { TransportProtocol::Udp };
+        self.create_client_transaction_on(request, destination, transport, None)
+            .await
+    }
+
+    /// Create a new client transaction over `transport`, with the timers of
+    /// `trunk` when the request goes to one
+    pub async fn create_client_transaction_on(
+        &self,
+        request: SipRequest,
+        destination: SocketAddr,
+        transport: TransportProtocol,
+        trunk: Option<&str>,
+    ) -> Result<TransactionId, String> {
+        let is_reliable = transport != TransportProtocol::Udp;
+        let timers = self.timer_config().timers_for(transport, trunk);
         // Extract transaction ID from Via branch parameter
         let branch = extract_branch(request.headers())
             .ok_or_else(|| "No branch parameter in Via header".to_string())?;
@@ -795,7 +1121,9 @@ impl TransactionLayer {
             Transaction::new_invite_client(txn_id.clone(), request, destination, is_reliable)
         } else {
             Transaction::new_non_invite_client(txn_id.clone(), request, destination, is_reliable)
-        };
+        }
+        .with_timers(timers)
+        .with_clock(self.clock.clone());
 
         // Store transaction
         let mut txns = self.transactions.write().await;
@@ -815,6 +1143,21 @@ impl TransactionLayer {
         source: SocketAddr,
         is_reliable: bool,
     ) -> Result<TransactionId, String> {
+        let transport = if is_reliable { TransportProtocol::Tcp } else { TransportProtocol::Udp };
+        self.create_server_transaction_on(request, source, transport, None)
+            .await
+    }
+
+    /// Create a new server transaction for a request received over
+    /// `transport`, with the timers of `trunk` when it came from one
+    pub async fn create_server_transaction_on(
+        &self,
+        request: SipRequest,
+        source: SocketAddr,
+        transport: TransportProtocol,
+        trunk: Option<&str>,
+    ) -> Result<TransactionId, String> {
+        let is_reliable = transport != TransportProtocol::Udp;
         // Extract transaction ID from Via branch parameter
         let branch = extract_branch(request.headers())
             .ok_or_else(|| "No branch parameter in Via header".to_string())?;
@@ -836,7 +1179,9 @@ impl TransactionLayer {
             Transaction::new_invite_server(txn_id.clone(), request, source, is_reliable)
         } else {
             Transaction::new_non_invite_server(txn_id.clone(), request, source, is_reliable)
-        };
+        }
+        .with_timers(self.timer_config().timers_for(transport, trunk))
+        .with_clock(self.clock.clone());
 
         // Store transaction
         let mut txns = self.transactions.write().await;
@@ -1021,6 +1366,7 @@ impl Clone for Transaction {
             last_response: self.last_response.clone(),
             created_at: self.created_at,
             sip_timers: self.sip_timers,
+            clock: self.clock.clone(),
         }
     }
 }
@@ -1537,4 +1883,173 @@ mod tests {
             TransactionState::NonInviteClient(NonInviteClientState::Completed)
         ));
     }
+
+    #[tokio::test]
+    async fn test_timer_b_fires_on_manual_clock() {
+        use crate::test_support::{ManualClock, SipRequestBuilder};
+
+        let clock = Arc::new(ManualClock::new());
+        let layer = TransactionLayer::new().with_clock(clock.clone());
+        let invite = SipRequestBuilder::invite("sip:alice@example.com", "sip:bob@example.com").build();
+        let id = layer
+            .create_client_transaction(invite, "127.0.0.1:5060".parse().unwrap(), false)
+            .await
+            .unwrap();
+
+        // Timer A retransmits while Timer B (64*T1 = 32s) runs
+        clock.advance(Duration::from_secs(31));
+        layer.process_timers().await;
+        assert!(layer.has_transaction(&id).await);
+
+        clock.advance(Duration::from_secs(1));
+        layer.process_timers().await;
+        assert!(!layer.has_transaction(&id).await);
+    }
+
+    #[test]
+    fn test_custom_timers_schedule_retransmissions() {
+        use crate::test_support::{ManualClock, SipRequestBuilder};
+
+        let clock = Arc::new(ManualClock::new());
+        let timers = SipTimers {
+            t1: Duration::from_millis(100),
+            t2: Duration::from_millis(400),
+            timer_b_factor: 9,
+            ..SipTimers::default()
+        };
+        let invite = SipRequestBuilder::invite("sip:alice@example.com", "sip:bob@example.com").build();
+        let mut txn = Transaction::new_invite_client(
+            TransactionId::generate(),
+            invite,
+            "127.0.0.1:5060".parse().unwrap(),
+            false,
+        )
+        .with_timers(timers)
+        .with_clock(clock.clone());
+
+        // Timer A: 100ms, then doubling up to T2 = 400ms
+        for interval in [100, 200, 400] {
+            clock.advance(Duration::from_millis(interval - 1));
+            assert!(txn.check_timers().is_empty());
+            clock.advance(Duration::from_millis(1));
+            assert_eq!(
+                txn.check_timers(),
+                vec![(TimerType::TimerA, TransactionTimerAction::RetransmitRequest)]
+            );
+        }
+
+        // Timer B: 9*T1 = 900ms
+        clock.advance(Duration::from_millis(199));
+        assert!(txn.check_timers().is_empty());
+        clock.advance(Duration::from_millis(1));
+        assert_eq!(
+            txn.check_timers(),
+            vec![(TimerType::TimerB, TransactionTimerAction::Timeout)]
+        );
+        assert!(txn.state.is_terminated());
+    }
+
+    #[tokio::test]
+    async fn test_trunk_timers_and_reload() {
+        use crate::test_support::{ManualClock, SipRequestBuilder};
+
+        let mut config = SipTimerConfig {
+            t1_ms: 50,
+            timer_f_factor: 10,
+            ..SipTimerConfig::default()
+        };
+        config.tcp.timer_f_factor = Some(20);
+        config.trunks.insert(
+            "satellite".to_string(),
+            SipTimerOverrides {
+                t1_ms: Some(2000),
+                ..SipTimerOverrides::default()
+            },
+        );
+        assert!(config.validate().is_ok());
+
+        let clock = Arc::new(ManualClock::new());
+        let layer = TransactionLayer::new()
+            .with_clock(clock.clone())
+            .with_timer_config(config);
+        let dest: SocketAddr = "127.0.0.1:5060".parse().unwrap();
+        let register = || SipRequestBuilder::register("sip:alice@example.com").build();
+
+        let udp = layer
+            .create_client_transaction_on(register(), dest, TransportProtocol::Udp, None)
+            .await
+            .unwrap();
+        let tcp = layer
+            .create_client_transaction_on(register(), dest, TransportProtocol::Tcp, None)
+            .await
+            .unwrap();
+        let satellite = layer
+            .create_client_transaction_on(register(), dest, TransportProtocol::Tcp, Some("satellite"))
+            .await
+            .unwrap();
+
+        // A reload only affects transactions created afterwards
+        layer
+            .reload_timer_config(SipTimerConfig {
+                t1_ms: 10,
+                timer_f_factor: 10,
+                ..SipTimerConfig::default()
+            })
+            .unwrap();
+        let reloaded = layer
+            .create_client_transaction_on(register(), dest, TransportProtocol::Udp, None)
+            .await
+            .unwrap();
+
+        // Timer F: 10*10ms, 10*50ms, 20*50ms and 20*2000ms
+        clock.advance(Duration::from_millis(100));
+        layer.process_timers().await;
+        assert!(!layer.has_transaction(&reloaded).await);
+        assert!(layer.has_transaction(&udp).await);
+
+        clock.advance(Duration::from_millis(400));
+        layer.process_timers().await;
+        assert!(!layer.has_transaction(&udp).await);
+        assert!(layer.has_transaction(&tcp).await);
+
+        clock.advance(Duration::from_millis(500));
+        layer.process_timers().await;
+        assert!(!layer.has_transaction(&tcp).await);
+        assert!(layer.has_transaction(&satellite).await);
+
+        clock.advance(Duration::from_millis(39_000));
+        layer.process_timers().await;
+        assert!(!layer.has_transaction(&satellite).await);
+    }
+
+    #[test]
+    fn test_timer_config_validation() {
+        assert!(SipTimerConfig::default().validate().is_ok());
+
+        let config = SipTimerConfig {
+            t1_ms: 5000,
+            ..SipTimerConfig::default()
+        };
+        assert!(config.validate().unwrap_err().contains("t2"));
+
+        let mut config = SipTimerConfig::default();
+        config.udp.t4_ms = Some(0);
+        assert!(config.validate().unwrap_err().starts_with("udp: t4"));
+
+        let mut config = SipTimerConfig::default();
+        config.trunks.insert(
+            "slow".to_string(),
+            SipTimerOverrides {
+                timer_b_factor: Some(0),
+                ..SipTimerOverrides::default()
+            },
+        );
+        assert!(config.validate().unwrap_err().starts_with("trunks.slow"));
+
+        let effective = SipTimerConfig::default().effective();
+        assert_eq!(effective.len(), 3);
+        assert_eq!(effective[0].timer_b_ms, 32_000);
+        assert_eq!(effective[0].timer_d_ms, 32_000);
+        assert_eq!(effective[1].timer_d_ms, 0);
+    }
 }
@@ -0,0 +1,407 @@
+//! Blind transfer with recovery
+//!
+//! Once a REFER is accepted the transferee waits on music on hold while the
+//! transfer target is invited. The transferor follows the attempt through
+//! `refer` NOTIFYs carrying a `message/sipfrag` body (RFC 3515). When the
+//! target rejects the call or does not answer in time, the transferee is not
+//! dropped: the call is offered back to the transferor, or to a configured
+//! fallback such as the transferor's voicemail, even if the transferor has
+//! already hung up.
+//!
+//! The NOTIFYs belong to the REFER's implicit subscription
+//! ([`ReferSubscription`]): it expires unless refreshed by a SUBSCRIBE,
+//! statuses are reported in order and only once, and the final status
+//! terminates it with `reason=noresource`. A transferor that sent
+//! `Refer-Sub: false` (RFC 4488) gets no subscription and no NOTIFYs.
+
+use super::call_state::CallLeg;
+use super::message::{SipError, SipRequest};
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+use std::time::{Duration, Instant};
+use uuid::Uuid;
+
+/// How long a transfer target and the recovery destination may ring
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct TransferPolicy {
+    /// Seconds the transfer target has to answer
+    pub timeout_secs: u64,
+    /// Send the transferee here instead of re-ringing the transferor when
+    /// the target fails; `{user}` is replaced by the transferor's username
+    /// (e.g. `sip:vm-{user}@pbx.example.com`)
+    pub fallback_uri: Option<String>,
+    /// Seconds the REFER's implicit subscription lasts unless refreshed
+    pub subscription_secs: u64,
+}
+
+impl Default for TransferPolicy {
+    fn default() -> Self {
+        Self {
+            timeout_secs: 30,
+            fallback_uri: None,
+            subscription_secs: 60,
+        }
+    }
+}
+
+impl TransferPolicy {
+    pub fn timeout(&self) -> Duration {
+        Duration::from_secs(self.timeout_secs)
+    }
+
+    pub fn subscription_expires(&self) -> Duration {
+        Duration::from_secs(self.subscription_secs)
+    }
+
+    /// Where the transferee goes when a transfer by `transferor_uri` fails
+    pub fn recovery_target(&self, transferor_uri: &str) -> String {
+        match &self.fallback_uri {
+            Some(fallback) => {
+                let user = transferor_uri
+                    .trim_start_matches("sip:")
+                    .trim_start_matches("sips:")
+                    .split('@')
+                    .next()
+                    .unwrap_or_default();
+                fallback.replace("{user}", user)
+            }
+            None => transferor_uri.to_string(),
+        }
+    }
+}
+
+/// Transfer waiting for its target to answer
+#[derive(Debug, Clone)]
+pub struct PendingTransfer {
+    pub target: String,
+    /// Leg that sent the REFER
+    pub transferor: CallLeg,
+    pub transferor_uri: String,
+    /// Where the transferee goes if the target fails
+    pub recovery_target: String,
+    /// The target counts as not answering after this
+    pub deadline: Instant,
+    /// The transferor has hung up
+    pub transferor_released: bool,
+    /// Implicit subscription of the REFER; `None` with `Refer-Sub: false`
+    pub subscription: Option<ReferSubscription>,
+}
+
+impl PendingTransfer {
+    pub fn new(
+        target: String,
+        transferor: CallLeg,
+        transferor_uri: String,
+        policy: &TransferPolicy,
+    ) -> Self {
+        Self {
+            target,
+            recovery_target: policy.recovery_target(&transferor_uri),
+            transferor,
+            transferor_uri,
+            deadline: Instant::now() + policy.timeout(),
+            transferor_released: false,
+            subscription: Some(ReferSubscription::new(policy.subscription_expires())),
+        }
+    }
+
+    pub fn is_expired(&self) -> bool {
+        Instant::now() >= self.deadline
+    }
+
+    /// Time left for the target to answer
+    pub fn remaining(&self) -> Duration {
+        self.deadline.saturating_duration_since(Instant::now())
+    }
+}
+
+/// How a transfer ended
+#[derive(Debug, Clone, PartialEq)]
+pub enum TransferOutcome {
+    /// The target answered and replaced the transferor
+    Completed { target: String },
+    /// The target failed with `failed_status` (408 when it did not answer
+    /// in time) and the transferee was reconnected to `target`
+    Recovered { target: String, failed_status: u16 },
+    /// Neither the target nor the recovery destination answered; the call
+    /// was ended
+    Failed { failed_status: u16 },
+}
+
+/// Why a subscription ended (RFC 6665 `reason` parameter)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SubscriptionEndReason {
+    /// The transfer concluded; there is nothing left to report
+    NoResource,
+    /// The subscription expired or was ended with `Expires: 0`
+    Timeout,
+}
+
+impl SubscriptionEndReason {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            SubscriptionEndReason::NoResource => "noresource",
+            SubscriptionEndReason::Timeout => "timeout",
+        }
+    }
+}
+
+/// A `refer` NOTIFY to send to the transferor
+#[derive(Debug, Clone, PartialEq)]
+pub struct ReferNotify {
+    /// `message/sipfrag` body
+    pub sipfrag: String,
+    /// Subscription-State header value
+    pub subscription_state: String,
+    /// Position of the NOTIFY in the subscription, from 1
+    pub sequence: u32,
+}
+
+impl ReferNotify {
+    /// This NOTIFY ends the subscription
+    pub fn is_terminated(&self) -> bool {
+        self.subscription_state.starts_with("terminated")
+    }
+}
+
+/// Implicit subscription created by an accepted REFER
+#[derive(Debug, Clone)]
+pub struct ReferSubscription {
+    expires_at: Instant,
+    /// Status last reported to the transferor
+    last_status: Option<u16>,
+    sequence: u32,
+    ended: Option<SubscriptionEndReason>,
+}
+
+impl ReferSubscription {
+    pub fn new(expires: Duration) -> Self {
+        Self {
+            expires_at: Instant::now() + expires,
+            last_status: None,
+            sequence: 0,
+            ended: None,
+        }
+    }
+
+    pub fn is_terminated(&self) -> bool {
+        self.ended.is_some()
+    }
+
+    pub fn is_expired(&self) -> bool {
+        Instant::now() >= self.expires_at
+    }
+
+    /// NOTIFY reporting target status `status`, if one is due
+    ///
+    /// Nothing is sent once the subscription has ended, and a provisional
+    /// status is sent only when it moves past the last one reported. A
+    /// final status ends the subscription with `reason=noresource`; an
+    /// expired subscription is ended with `reason=timeout` instead.
+    pub fn notify(&mut self, status: u16) -> Option<ReferNotify> {
+        if self.is_terminated() {
+            return None;
+        }
+        if self.is_expired() {
+            return Some(self.end(status, SubscriptionEndReason::Timeout));
+        }
+        if status >= 200 {
+            return Some(self.end(status, SubscriptionEndReason::NoResource));
+        }
+        if self.last_status.is_some_and(|last| status <= last) {
+            return None;
+        }
+        Some(self.active(status))
+    }
+
+    /// Refresh by SUBSCRIBE; `Expires: 0` ends the subscription
+    ///
+    /// Returns the NOTIFY that answers the SUBSCRIBE, carrying the last
+    /// reported status; fails once the subscription has ended.
+    pub fn refresh(&mut self, expires: Duration) -> Result<ReferNotify, String> {
+        if self.is_terminated() {
+            return Err("Subscription has ended".to_string());
+        }
+        let status = self.last_status.unwrap_or(100);
+        if expires.is_zero() || self.is_expired() {
+            return Ok(self.end(status, SubscriptionEndReason::Timeout));
+        }
+        self.expires_at = Instant::now() + expires;
+        Ok(self.active(status))
+    }
+
+    fn active(&mut self, status: u16) -> ReferNotify {
+        let remaining = self.expires_at.saturating_duration_since(Instant::now());
+        let state = format!("active;expires={}", remaining.as_secs());
+        self.next(status, state)
+    }
+
+    fn end(&mut self, status: u16, reason: SubscriptionEndReason) -> ReferNotify {
+        self.ended = Some(reason);
+        self.next(status, format!("terminated;reason={}", reason.as_str()))
+    }
+
+    fn next(&mut self, status: u16, subscription_state: String) -> ReferNotify {
+        self.last_status = Some(status);
+        self.sequence += 1;
+        ReferNotify {
+            sipfrag: sipfrag(status),
+            subscription_state,
+            sequence: self.sequence,
+        }
+    }
+}
+
+/// Reports transfer progress to the transferor
+#[async_trait]
+pub trait TransferNotifier: Send + Sync {
+    /// Send a `refer` NOTIFY with `notify.sipfrag` as `message/sipfrag`
+    /// body and `notify.subscription_state` as Subscription-State
+    async fn notify(
+        &self,
+        call_id: &str,
+        transferor_uri: &str,
+        notify: &ReferNotify,
+    ) -> Result<(), SipError>;
+}
+
+/// `message/sipfrag` status line for `status`
+pub fn sipfrag(status: u16) -> String {
+    let reason = match status {
+        100 => "Trying",
+        180 => "Ringing",
+        200 => "OK",
+        404 => "Not Found",
+        408 => "Request Timeout",
+        480 => "Temporarily Unavailable",
+        486 => "Busy Here",
+        487 => "Request Terminated",
+        500 => "Server Internal Error",
+        503 => "Service Unavailable",
+        603 => "Decline",
+        _ => match status / 100 {
+            1 => "Session Progress",
+            2 => "OK",
+            4 => "Request Failure",
+            5 => "Server Failure",
+            _ => "Global Failure",
+        },
+    };
+    format!("SIP/2.0 {} {}", status, reason)
+}
+
+/// URI of a Refer-To header value (`<sip:bob@example.com;transport=tcp>`)
+pub fn refer_to_uri(refer_to: &str) -> String {
+    let value = refer_to.trim();
+    match (value.find('<'), value.find('>')) {
+        (Some(start), Some(end)) if start < end => value[start + 1..end].to_string(),
+        _ => value.split(';').next().unwrap_or(value).to_string(),
+    }
+}
+
+/// INVITE to `target` on behalf of the transferee
+pub fn transfer_invite(
+    call_id: &str,
+    transferee_uri: &str,
+    transferor_uri: &str,
+    target: &str,
+) -> Result<SipRequest, SipError> {
+    let request = format!(
+        "INVITE {target} SIP/2.0\r\n\
+         Max-Forwards: 70\r\n\
+         From: <{transferee}>;tag={tag}\r\n\
+         To: <{target}>\r\n\
+         Call-ID: {call_id}-xfer-{suffix}\r\n\
+         CSeq: 1 INVITE\r\n\
+         Referred-By: <{transferor}>\r\n\
+         Content-Length: 0\r\n\r\n",
+        target = target,
+        transferee = transferee_uri,
+        transferor = transferor_uri,
+        tag = Uuid::new_v4().simple(),
+        call_id = call_id,
+        suffix = Uuid::new_v4().simple(),
+    );
+    SipRequest::parse(request.as_bytes())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_recovery_target() {
+        let policy = TransferPolicy::default();
+        assert_eq!(
+            policy.recovery_target("sip:bob@example.com"),
+            "sip:bob@example.com"
+        );
+
+        let policy = TransferPolicy {
+            fallback_uri: Some("sip:vm-{user}@example.com".to_string()),
+            ..Default::default()
+        };
+        assert_eq!(
+            policy.recovery_target("sip:bob@example.com"),
+            "sip:vm-bob@example.com"
+        );
+    }
+
+    #[test]
+    fn test_sipfrag_and_refer_to() {
+        assert_eq!(sipfrag(100), "SIP/2.0 100 Trying");
+        assert_eq!(sipfrag(486), "SIP/2.0 486 Busy Here");
+        assert_eq!(sipfrag(599), "SIP/2.0 599 Server Failure");
+
+        assert_eq!(
+            refer_to_uri("\"Charlie\" <sip:charlie@example.com;transport=tcp>"),
+            "sip:charlie@example.com;transport=tcp"
+        );
+        assert_eq!(
+            refer_to_uri("sip:charlie@example.com"),
+            "sip:charlie@example.com"
+        );
+    }
+
+    #[test]
+    fn test_refer_subscription_sequencing() {
+        let mut subscription = ReferSubscription::new(Duration::from_secs(60));
+
+        let trying = subscription.notify(100).unwrap();
+        assert_eq!(trying.sipfrag, "SIP/2.0 100 Trying");
+        assert!(trying.subscription_state.starts_with("active;expires="));
+        let ringing = subscription.notify(180).unwrap();
+        assert_eq!(ringing.sequence, 2);
+        // Repeated or older provisional statuses are not reported again
+        assert!(subscription.notify(180).is_none());
+        assert!(subscription.notify(100).is_none());
+
+        let busy = subscription.notify(486).unwrap();
+        assert_eq!(busy.sipfrag, "SIP/2.0 486 Busy Here");
+        assert_eq!(busy.subscription_state, "terminated;reason=noresource");
+        assert_eq!(busy.sequence, 3);
+        assert!(subscription.notify(200).is_none());
+        assert!(subscription.refresh(Duration::from_secs(60)).is_err());
+    }
+
+    #[test]
+    fn test_refer_subscription_refresh_and_expiry() {
+        let mut subscription = ReferSubscription::new(Duration::from_secs(60));
+        subscription.notify(100).unwrap();
+
+        let refreshed = subscription.refresh(Duration::from_secs(120)).unwrap();
+        assert_eq!(refreshed.sipfrag, "SIP/2.0 100 Trying");
+        assert!(refreshed.subscription_state.starts_with("active;expires=1"));
+
+        let ended = subscription.refresh(Duration::ZERO).unwrap();
+        assert_eq!(ended.subscription_state, "terminated;reason=timeout");
+        assert!(subscription.notify(180).is_none());
+
+        // An expired subscription ends with the next status
+        let mut expired = ReferSubscription::new(Duration::ZERO);
+        let notify = expired.notify(180).unwrap();
+        assert_eq!(notify.subscription_state, "terminated;reason=timeout");
+        assert!(expired.notify(200).is_none());
+    }
+}
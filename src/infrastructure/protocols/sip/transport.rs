@@ -1,12 +1,17 @@
 //! SIP transport layer - handles UDP, TCP, TLS, WebSocket
 
-use super::message::{SipError, SipMessage};
+use super::address::{uri_socket_addr, via_sent_by};
+use super::connection::{
+    classify_keepalive, ConnectionConfig, ConnectionTable, KeepAlive, KEEPALIVE_PONG,
+};
+use super::message::{SipError, SipMessage, SipRequest};
 use bytes::Bytes;
 use rustls::{ClientConfig, ServerConfig};
 use rustls::client::danger::{HandshakeSignatureValid, ServerCertVerified, ServerCertVerifier};
 use rustls::pki_types::{CertificateDer, ServerName, UnixTime};
 use rustls::DigitallySignedStruct;
 use rustls_pemfile::{certs, rsa_private_keys};
+use serde::{Deserialize, Serialize};
 use std::fs::File;
 use std::io::BufReader;
 use std::net::SocketAddr;
@@ -14,11 +19,13 @@ use std::path::Path;
 use std::sync::Arc;
 use tokio::net::{TcpListener, TcpStream, UdpSocket};
 use tokio::sync::mpsc;
+use tokio::task::JoinHandle;
 use tokio_rustls::{TlsAcceptor, TlsConnector};
 use tracing::{debug, error, info, warn};
 
 /// Custom certificate verifier that accepts any certificate
 /// Used for SIP where self-signed certificates are common
+#[derive(Debug)]
 struct NoCertificateVerification;
 
 impl ServerCertVerifier for NoCertificateVerification {
@@ -61,7 +68,8 @@ impl ServerCertVerifier for NoCertificateVerification {
 }
 
 /// Transport protocol type
-#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "UPPERCASE")]
 pub enum TransportProtocol {
     Udp,
     Tcp,
@@ -128,6 +136,7 @@ pub trait Transport: Send + Sync {
 pub struct UdpTransport {
     bind_addr: SocketAddr,
     pub socket: Option<Arc<UdpSocket>>,
+    receive_task: Option<JoinHandle<()>>,
     tx: mpsc::Sender<IncomingMessage>,
     rx: mpsc::Receiver<IncomingMessage>,
 }
@@ -138,6 +147,7 @@ impl UdpTransport {
         Self {
             bind_addr,
             socket: None,
+            receive_task: None,
             tx,
             rx,
         }
@@ -194,15 +204,18 @@ impl Transport for UdpTransport {
 
         // Start receive loop in background
         let tx = self.tx.clone();
-        tokio::spawn(async move {
+        self.receive_task = Some(tokio::spawn(async move {
             Self::receive_loop(socket, tx).await;
-        });
+        }));
 
         Ok(())
     }
 
     async fn stop(&mut self) -> Result<(), SipError> {
         info!("Stopping UDP transport");
+        if let Some(task) = self.receive_task.take() {
+            task.abort();
+        }
         self.socket = None;
         Ok(())
     }
@@ -235,42 +248,126 @@ impl Transport for UdpTransport {
 /// TCP transport implementation
 pub struct TcpTransport {
     bind_addr: SocketAddr,
-    listener: Option<TcpListener>,
+    local_addr: Option<SocketAddr>,
+    accept_task: Option<JoinHandle<()>>,
+    connections: Arc<ConnectionTable>,
     tx: mpsc::Sender<IncomingMessage>,
     rx: mpsc::Receiver<IncomingMessage>,
 }
 
 impl TcpTransport {
     pub fn new(bind_addr: SocketAddr) -> Self {
+        Self::with_connection_config(bind_addr, ConnectionConfig::default())
+    }
+
+    /// Create TCP transport with custom connection management settings
+    pub fn with_connection_config(bind_addr: SocketAddr, config: ConnectionConfig) -> Self {
         let (tx, rx) = mpsc::channel(1000);
         Self {
             bind_addr,
-            listener: None,
+            local_addr: None,
+            accept_task: None,
+            connections: Arc::new(ConnectionTable::new(config)),
             tx,
             rx,
         }
     }
 
+    /// Get the connection table shared with the server
+    pub fn connections(&self) -> Arc<ConnectionTable> {
+        self.connections.clone()
+    }
+
+    /// Actual listening address (available after start)
+    pub fn local_addr(&self) -> Option<SocketAddr> {
+        self.local_addr
+    }
+
+    /// Register a stream in the connection table and spawn its reader and writer tasks
+    ///
+    /// Returns the writer channel, or None if the connection limit refused it.
+    async fn spawn_connection(
+        stream: TcpStream,
+        source: SocketAddr,
+        tx: mpsc::Sender<IncomingMessage>,
+        connections: Arc<ConnectionTable>,
+    ) -> Option<mpsc::Sender<Bytes>> {
+        use tokio::io::AsyncWriteExt;
+
+        let (reader, mut writer) = stream.into_split();
+        let (writer_tx, mut writer_rx) = mpsc::channel::<Bytes>(100);
+
+        if !connections.insert(source, writer_tx.clone()).await {
+            return None;
+        }
+
+        let write_task = tokio::spawn(async move {
+            while let Some(data) = writer_rx.recv().await {
+                if let Err(e) = writer.write_all(&data).await {
+                    error!("Failed to write to TCP connection {}: {}", source, e);
+                    break;
+                }
+                let _ = writer.flush().await;
+            }
+        });
+
+        let writer = writer_tx.clone();
+        let table = connections.clone();
+        let read_task = tokio::spawn(async move {
+            Self::handle_connection(reader, source, tx, writer, table.clone()).await;
+            table.remove(&source).await;
+        });
+
+        // Eviction, idle reaping and shutdown abort both tasks
+        if !connections
+            .set_tasks(&source, vec![read_task.abort_handle(), write_task.abort_handle()])
+            .await
+        {
+            return None;
+        }
+
+        Some(writer_tx)
+    }
+
     async fn handle_connection(
-        mut stream: TcpStream,
+        mut reader: tokio::net::tcp::OwnedReadHalf,
         source: SocketAddr,
         tx: mpsc::Sender<IncomingMessage>,
+        writer: mpsc::Sender<Bytes>,
+        connections: Arc<ConnectionTable>,
     ) {
         use tokio::io::AsyncReadExt;
 
         let mut buf = vec![0u8; 65535];
 
         loop {
-            match stream.read(&mut buf).await {
+            match reader.read(&mut buf).await {
                 Ok(0) => {
                     debug!("TCP connection closed by {}", source);
                     break;
                 }
                 Ok(size) => {
                     debug!("Received {} bytes from {} via TCP", size, source);
+                    connections.touch(&source).await;
+
+                    match classify_keepalive(&buf[..size]) {
+                        Some(KeepAlive::Ping) => {
+                            debug!("CRLF keep-alive ping from {}", source);
+                            let _ = writer.send(Bytes::from_static(KEEPALIVE_PONG)).await;
+                            continue;
+                        }
+                        Some(KeepAlive::Pong) => continue,
+                        None => {}
+                    }
 
                     match SipMessage::parse(&buf[..size]) {
                         Ok(message) => {
+                            if let SipMessage::Request(request) = &message {
+                                for alias in learned_aliases(request) {
+                                    connections.add_alias(alias, source).await;
+                                }
+                            }
+
                             let incoming = IncomingMessage {
                                 message,
                                 source,
@@ -295,15 +392,21 @@ impl TcpTransport {
         }
     }
 
-    async fn accept_loop(listener: TcpListener, tx: mpsc::Sender<IncomingMessage>) {
+    async fn accept_loop(
+        listener: TcpListener,
+        tx: mpsc::Sender<IncomingMessage>,
+        connections: Arc<ConnectionTable>,
+    ) {
         loop {
             match listener.accept().await {
                 Ok((stream, source)) => {
                     info!("Accepted TCP connection from {}", source);
-                    let tx = tx.clone();
-                    tokio::spawn(async move {
-                        Self::handle_connection(stream, source, tx).await;
-                    });
+                    if Self::spawn_connection(stream, source, tx.clone(), connections.clone())
+                        .await
+                        .is_none()
+                    {
+                        warn!("Rejected TCP connection from {}: connection limit reached", source);
+                    }
                 }
                 Err(e) => {
                     error!("Failed to accept TCP connection: {}", e);
@@ -314,6 +417,37 @@ impl TcpTransport {
     }
 }
 
+/// Addresses a request's sender advertises for itself (top Via sent-by and Contact)
+///
+/// These are learned as aliases of the connection the request arrived on, so
+/// requests later routed to the registered Contact reuse the same connection.
+/// Only an authenticated REGISTER may re-point an alias already in use.
+pub(super) fn learned_aliases(request: &SipRequest) -> Vec<SocketAddr> {
+    use rsip::headers::UntypedHeader;
+    use rsip::Header;
+
+    let mut aliases = Vec::new();
+
+    if let Some(via) = request.headers().iter().find_map(|h| match h {
+        Header::Via(via) => Some(via.to_string()),
+        _ => None,
+    }) {
+        if let Some(addr) = via_sent_by(&via) {
+            aliases.push(addr);
+        }
+    }
+
+    for header in request.headers().iter() {
+        if let Header::Contact(contact) = header {
+            if let Some(addr) = uri_socket_addr(contact.value()) {
+                aliases.push(addr);
+            }
+        }
+    }
+
+    aliases
+}
+
 #[async_trait::async_trait]
 impl Transport for TcpTransport {
     async fn start(&mut self) -> Result<(), SipError> {
@@ -323,48 +457,66 @@ impl Transport for TcpTransport {
             .await
             .map_err(|e| SipError::TransportError(format!("Failed to bind TCP socket: {}", e)))?;
 
-        info!("TCP transport listening on {}", listener.local_addr().unwrap());
+        let local_addr = listener.local_addr().unwrap();
+        info!("TCP transport listening on {}", local_addr);
+        self.local_addr = Some(local_addr);
 
         // Start accept loop in background
         let tx = self.tx.clone();
-        tokio::spawn(async move {
-            Self::accept_loop(listener, tx).await;
-        });
+        let connections = self.connections.clone();
+        self.accept_task = Some(tokio::spawn(async move {
+            Self::accept_loop(listener, tx, connections).await;
+        }));
+
+        self.connections.spawn_reaper();
 
         Ok(())
     }
 
     async fn stop(&mut self) -> Result<(), SipError> {
+        // Open connections keep being served until closed on their own
         info!("Stopping TCP transport");
-        self.listener = None;
+        if let Some(task) = self.accept_task.take() {
+            task.abort();
+        }
         Ok(())
     }
 
     async fn send(&self, message: OutgoingMessage) -> Result<(), SipError> {
-        use tokio::io::AsyncWriteExt;
-
         debug!(
             "Sending {} bytes to {} via TCP",
             message.data.len(),
             message.destination
         );
 
-        let mut stream = TcpStream::connect(message.destination)
-            .await
-            .map_err(|e| {
-                SipError::TransportError(format!("Failed to connect to {}: {}", message.destination, e))
-            })?;
+        // Reuse an existing connection (direct or via learned alias) if we have one
+        let writer = match self.connections.writer_for(&message.destination).await {
+            Some(writer) => writer,
+            None => {
+                let stream = TcpStream::connect(message.destination)
+                    .await
+                    .map_err(|e| {
+                        SipError::TransportError(format!("Failed to connect to {}: {}", message.destination, e))
+                    })?;
+
+                Self::spawn_connection(
+                    stream,
+                    message.destination,
+                    self.tx.clone(),
+                    self.connections.clone(),
+                )
+                .await
+                .ok_or_else(|| {
+                    SipError::TransportError("TCP connection limit reached".to_string())
+                })?
+            }
+        };
 
-        stream
-            .write_all(&message.data)
+        writer
+            .send(message.data)
             .await
             .map_err(|e| SipError::TransportError(format!("Failed to send TCP data: {}", e)))?;
 
-        stream
-            .flush()
-            .await
-            .map_err(|e| SipError::TransportError(format!("Failed to flush TCP stream: {}", e)))?;
-
         Ok(())
     }
 
@@ -378,7 +530,8 @@ pub struct TlsTransport {
     bind_addr: SocketAddr,
     cert_path: String,
     key_path: String,
-    listener: Option<TcpListener>,
+    local_addr: Option<SocketAddr>,
+    accept_task: Option<JoinHandle<()>>,
     acceptor: Option<TlsAcceptor>,
     tx: mpsc::Sender<IncomingMessage>,
     rx: mpsc::Receiver<IncomingMessage>,
@@ -391,13 +544,19 @@ impl TlsTransport {
             bind_addr,
             cert_path,
             key_path,
-            listener: None,
+            local_addr: None,
+            accept_task: None,
             acceptor: None,
             tx,
             rx,
         }
     }
 
+    /// Actual listening address (available after start)
+    pub fn local_addr(&self) -> Option<SocketAddr> {
+        self.local_addr
+    }
+
     /// Load TLS server configuration from certificate and key files
     fn load_tls_config(cert_path: &str, key_path: &str) -> Result<ServerConfig, SipError> {
         // Load certificate chain
@@ -541,22 +700,24 @@ impl Transport for TlsTransport {
             .await
             .map_err(|e| SipError::TransportError(format!("Failed to bind TLS socket: {}", e)))?;
 
-        info!("TLS transport listening on {}", listener.local_addr().unwrap());
-
-        self.listener = Some(listener.try_clone().await.unwrap());
+        let local_addr = listener.local_addr().unwrap();
+        info!("TLS transport listening on {}", local_addr);
+        self.local_addr = Some(local_addr);
 
         // Start accept loop in background
         let tx = self.tx.clone();
-        tokio::spawn(async move {
+        self.accept_task = Some(tokio::spawn(async move {
             Self::accept_loop(listener, acceptor, tx).await;
-        });
+        }));
 
         Ok(())
     }
 
     async fn stop(&mut self) -> Result<(), SipError> {
         info!("Stopping TLS transport");
-        self.listener = None;
+        if let Some(task) = self.accept_task.take() {
+            task.abort();
+        }
         self.acceptor = None;
         Ok(())
     }
@@ -627,6 +788,7 @@ impl Transport for TlsTransport {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::infrastructure::protocols::sip::connection::KEEPALIVE_PING;
 
     #[tokio::test]
     async fn test_udp_transport_start() {
@@ -652,6 +814,89 @@ mod tests {
         transport.stop().await.unwrap();
     }
 
+    #[tokio::test]
+    async fn test_tcp_connection_reused_after_register() {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        let mut transport = TcpTransport::new("127.0.0.1:0".parse().unwrap());
+        transport.start().await.unwrap();
+        let server_addr = transport.local_addr().unwrap();
+
+        // Phone connects and registers with a Contact it cannot be reached on directly
+        let mut phone = TcpStream::connect(server_addr).await.unwrap();
+        let register = "REGISTER sip:example.com SIP/2.0\r\n\
+            Via: SIP/2.0/TCP 127.0.0.1:5099;branch=z9hG4bKreg1\r\n\
+            From: Alice <sip:alice@example.com>;tag=1\r\n\
+            To: Alice <sip:alice@example.com>\r\n\
+            Call-ID: reg-1\r\n\
+            CSeq: 1 REGISTER\r\n\
+            Contact: <sip:alice@127.0.0.1:5099;transport=tcp>\r\n\
+            Content-Length: 0\r\n\r\n";
+        phone.write_all(register.as_bytes()).await.unwrap();
+
+        let incoming = transport.receiver().recv().await.unwrap();
+        assert!(incoming.message.is_request());
+        assert_eq!(transport.connections().len().await, 1);
+
+        // Keep-alive ping is answered with a pong
+        phone.write_all(KEEPALIVE_PING).await.unwrap();
+        let mut pong = [0u8; 2];
+        phone.read_exact(&mut pong).await.unwrap();
+        assert_eq!(&pong, b"\r\n");
+
+        // An INVITE routed to the registered Contact reuses the phone's connection
+        let invite = Bytes::from_static(b"INVITE sip:alice@127.0.0.1:5099 SIP/2.0\r\n\r\n");
+        transport
+            .send(OutgoingMessage {
+                data: invite.clone(),
+                destination: "127.0.0.1:5099".parse().unwrap(),
+                protocol: TransportProtocol::Tcp,
+            })
+            .await
+            .unwrap();
+
+        let mut buf = vec![0u8; invite.len()];
+        phone.read_exact(&mut buf).await.unwrap();
+        assert_eq!(buf, invite.to_vec());
+        assert_eq!(transport.connections().len().await, 1);
+    }
+
+    #[tokio::test]
+    async fn test_tcp_evicted_and_reaped_connections_are_closed() {
+        use tokio::io::AsyncReadExt;
+        use std::time::Duration;
+        use tokio::time::{sleep, timeout};
+
+        let config = ConnectionConfig {
+            idle_timeout: Duration::ZERO,
+            max_connections: 1,
+            reap_interval: Duration::from_secs(3600),
+        };
+        let mut transport =
+            TcpTransport::with_connection_config("127.0.0.1:0".parse().unwrap(), config);
+        transport.start().await.unwrap();
+        let server_addr = transport.local_addr().unwrap();
+
+        let mut first = TcpStream::connect(server_addr).await.unwrap();
+        while transport.connections().is_empty().await {
+            sleep(Duration::from_millis(10)).await;
+        }
+
+        // A second connection evicts the first, whose peer sees EOF
+        let mut second = TcpStream::connect(server_addr).await.unwrap();
+        let mut buf = [0u8; 16];
+        let read = timeout(Duration::from_secs(5), first.read(&mut buf)).await.unwrap();
+        assert_eq!(read.unwrap(), 0);
+
+        // Reaping the idle second connection closes it too
+        while transport.connections().reap_idle().await.is_empty() {
+            sleep(Duration::from_millis(10)).await;
+        }
+        let read = timeout(Duration::from_secs(5), second.read(&mut buf)).await.unwrap();
+        assert_eq!(read.unwrap(), 0);
+        assert!(transport.connections().is_empty().await);
+    }
+
     #[tokio::test]
     async fn test_tls_transport_missing_cert() {
         let bind_addr = "127.0.0.1:5061".parse().unwrap();
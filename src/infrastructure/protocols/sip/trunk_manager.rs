@@ -0,0 +1,423 @@
+//! Channel accounting of trunks
+//!
+//! A trunk with a limited number of channels can keep some of them for
+//! each direction: with 30 channels and 5 reserved for inbound calls,
+//! outbound calls never take more than 25, so the main number stays
+//! reachable during an outbound burst. A channel is taken when a call
+//! starts on the trunk and given back when the call is released, however
+//! it ends. An outbound call finding its trunk full fails over to the next
+//! trunk of `failover` that has a channel, or is rejected with 503.
+//!
+//! Trunks are named as in the header rules (`sip.header_rules.trunks`);
+//! calls on trunks without limits are counted but never refused.
+
+use metrics::gauge;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::sync::Mutex;
+use thiserror::Error;
+use tracing::{debug, warn};
+
+/// Channel limits of one trunk
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct TrunkChannelLimits {
+    /// Channels of the trunk
+    pub total: usize,
+    /// Channels outbound calls may not take
+    pub reserved_inbound: usize,
+    /// Channels inbound calls may not take
+    pub reserved_outbound: usize,
+    /// Host outbound calls failed over to this trunk are sent to
+    pub host: Option<String>,
+}
+
+/// Channel limits of all trunks
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct TrunkChannelConfig {
+    /// Limits by trunk name
+    pub trunks: BTreeMap<String, TrunkChannelLimits>,
+    /// Trunks tried in order when an outbound call's trunk is full
+    pub failover: Vec<String>,
+}
+
+impl TrunkChannelConfig {
+    pub fn validate(&self) -> Result<(), String> {
+        for (name, limits) in &self.trunks {
+            if limits.total == 0 {
+                return Err(format!("trunk {}: total channels must be greater than 0", name));
+            }
+            if limits.reserved_inbound + limits.reserved_outbound > limits.total {
+                return Err(format!(
+                    "trunk {}: {} inbound and {} outbound reserved channels exceed its {} channels",
+                    name, limits.reserved_inbound, limits.reserved_outbound, limits.total
+                ));
+            }
+        }
+        for name in &self.failover {
+            match self.trunks.get(name) {
+                Some(limits) if limits.host.is_some() => {}
+                Some(_) => return Err(format!("failover trunk {} has no host", name)),
+                None => return Err(format!("failover trunk {} has no channel limits", name)),
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Direction of a call on a trunk
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ChannelDirection {
+    Inbound,
+    Outbound,
+}
+
+impl ChannelDirection {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Inbound => "inbound",
+            Self::Outbound => "outbound",
+        }
+    }
+}
+
+/// No channel of the trunk is free for the call
+#[derive(Debug, Clone, PartialEq, Eq, Error)]
+#[error("No {} channel free on trunk {trunk}", direction.as_str())]
+pub struct TrunkFull {
+    pub trunk: String,
+    pub direction: ChannelDirection,
+}
+
+/// Channels a trunk is using
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TrunkOccupancy {
+    pub trunk: String,
+    pub inbound: usize,
+    pub outbound: usize,
+    /// `None` for trunks without limits
+    pub limits: Option<TrunkChannelLimits>,
+}
+
+#[derive(Debug, Default, Clone, Copy)]
+struct InUse {
+    inbound: usize,
+    outbound: usize,
+}
+
+impl InUse {
+    fn get(&self, direction: ChannelDirection) -> usize {
+        match direction {
+            ChannelDirection::Inbound => self.inbound,
+            ChannelDirection::Outbound => self.outbound,
+        }
+    }
+
+    fn get_mut(&mut self, direction: ChannelDirection) -> &mut usize {
+        match direction {
+            ChannelDirection::Inbound => &mut self.inbound,
+            ChannelDirection::Outbound => &mut self.outbound,
+        }
+    }
+
+    /// Whether one more call of `direction` leaves every reservation of
+    /// the other direction satisfiable
+    fn admits(&self, limits: &TrunkChannelLimits, direction: ChannelDirection) -> bool {
+        let used = self.inbound + self.outbound;
+        if used >= limits.total {
+            return false;
+        }
+        let (other, reserved) = match direction {
+            ChannelDirection::Inbound => (ChannelDirection::Outbound, limits.reserved_outbound),
+            ChannelDirection::Outbound => (ChannelDirection::Inbound, limits.reserved_inbound),
+        };
+        limits.total - used > reserved.saturating_sub(self.get(other))
+    }
+}
+
+#[derive(Debug, Default)]
+struct ChannelState {
+    trunks: HashMap<String, InUse>,
+    /// Trunk and direction of each call holding a channel
+    calls: HashMap<String, (String, ChannelDirection)>,
+}
+
+/// Takes and gives back trunk channels for calls
+pub struct TrunkManager {
+    config: TrunkChannelConfig,
+    // One lock over every trunk: calls racing for the last channel are
+    // admitted one at a time
+    state: Mutex<ChannelState>,
+}
+
+impl TrunkManager {
+    pub fn new(config: TrunkChannelConfig) -> Self {
+        Self {
+            config,
+            state: Mutex::new(ChannelState::default()),
+        }
+    }
+
+    pub fn config(&self) -> &TrunkChannelConfig {
+        &self.config
+    }
+
+    /// Take a channel of `trunk` for `call_id`
+    ///
+    /// A call already holding a channel of the trunk keeps it; one holding
+    /// a channel of another trunk gives that back first.
+    pub fn acquire(
+        &self,
+        call_id: &str,
+        trunk: &str,
+        direction: ChannelDirection,
+    ) -> Result<(), TrunkFull> {
+        let mut state = self.state.lock().unwrap();
+        self.acquire_locked(&mut state, call_id, trunk, direction)
+    }
+
+    /// Take a channel for an outbound call on `trunk`, or on the first
+    /// failover trunk with one free; returns the trunk taken
+    pub fn acquire_outbound(&self, call_id: &str, trunk: &str) -> Result<String, TrunkFull> {
+        let mut state = self.state.lock().unwrap();
+        let candidates = std::iter::once(trunk)
+            .chain(self.config.failover.iter().map(String::as_str).filter(|t| *t != trunk));
+        for candidate in candidates {
+            if self
+                .acquire_locked(&mut state, call_id, candidate, ChannelDirection::Outbound)
+                .is_ok()
+            {
+                if candidate != trunk {
+                    warn!(
+                        "Call {}: trunk {} has no outbound channel free, failing over to {}",
+                        call_id, trunk, candidate
+                    );
+                }
+                return Ok(candidate.to_string());
+            }
+        }
+        Err(TrunkFull {
+            trunk: trunk.to_string(),
+            direction: ChannelDirection::Outbound,
+        })
+    }
+
+    /// Give back the channel of `call_id`, if it holds one
+    pub fn release(&self, call_id: &str) {
+        let mut state = self.state.lock().unwrap();
+        self.release_locked(&mut state, call_id);
+    }
+
+    /// Host outbound calls on `trunk` are sent to after a failover
+    pub fn host_of(&self, trunk: &str) -> Option<&str> {
+        self.config.trunks.get(trunk)?.host.as_deref()
+    }
+
+    /// Channels in use on every trunk with limits or calls
+    pub fn occupancy(&self) -> Vec<TrunkOccupancy> {
+        let state = self.state.lock().unwrap();
+        let mut occupancy: BTreeMap<&str, TrunkOccupancy> = self
+            .config
+            .trunks
+            .iter()
+            .map(|(trunk, limits)| {
+                (
+                    trunk.as_str(),
+                    TrunkOccupancy {
+                        trunk: trunk.clone(),
+                        inbound: 0,
+                        outbound: 0,
+                        limits: Some(limits.clone()),
+                    },
+                )
+            })
+            .collect();
+        for (trunk, in_use) in &state.trunks {
+            let entry = occupancy.entry(trunk).or_insert_with(|| TrunkOccupancy {
+                trunk: trunk.clone(),
+                inbound: 0,
+                outbound: 0,
+                limits: None,
+            });
+            entry.inbound = in_use.inbound;
+            entry.outbound = in_use.outbound;
+        }
+        occupancy.into_values().collect()
+    }
+
+    fn acquire_locked(
+        &self,
+        state: &mut ChannelState,
+        call_id: &str,
+        trunk: &str,
+        direction: ChannelDirection,
+    ) -> Result<(), TrunkFull> {
+        match state.calls.get(call_id) {
+            Some((held, _)) if held == trunk => return Ok(()),
+            Some(_) => self.release_locked(state, call_id),
+            None => {}
+        }
+
+        let in_use = state.trunks.entry(trunk.to_string()).or_default();
+        if let Some(limits) = self.config.trunks.get(trunk) {
+            if !in_use.admits(limits, direction) {
+                debug!(
+                    "Call {} refused on trunk {}: {}/{} channels in use ({} inbound)",
+                    call_id,
+                    trunk,
+                    in_use.inbound + in_use.outbound,
+                    limits.total,
+                    in_use.inbound
+                );
+                return Err(TrunkFull {
+                    trunk: trunk.to_string(),
+                    direction,
+                });
+            }
+        }
+        *in_use.get_mut(direction) += 1;
+        let count = in_use.get(direction);
+        state
+            .calls
+            .insert(call_id.to_string(), (trunk.to_string(), direction));
+        record_in_use(trunk, direction, count);
+        Ok(())
+    }
+
+    fn release_locked(&self, state: &mut ChannelState, call_id: &str) {
+        let Some((trunk, direction)) = state.calls.remove(call_id) else {
+            return;
+        };
+        if let Some(in_use) = state.trunks.get_mut(&trunk) {
+            let count = in_use.get_mut(direction);
+            *count = count.saturating_sub(1);
+            record_in_use(&trunk, direction, *count);
+        }
+    }
+}
+
+fn record_in_use(trunk: &str, direction: ChannelDirection, count: usize) {
+    gauge!(
+        "channels_in_use",
+        "trunk" => trunk.to_string(),
+        "direction" => direction.as_str()
+    )
+    .set(count as f64);
+}
+
+/// `uri` sent to `host` instead, keeping its user and parameters
+pub fn with_host(uri: &str, host: &str) -> String {
+    let (scheme, rest) = uri.split_once(':').unwrap_or(("sip", uri));
+    let (user, host_part) = match rest.rsplit_once('@') {
+        Some((user, host_part)) => (Some(user), host_part),
+        None => (None, rest),
+    };
+    let params = host_part
+        .find([';', '?'])
+        .map(|i| &host_part[i..])
+        .unwrap_or("");
+    match user {
+        Some(user) => format!("{}:{}@{}{}", scheme, user, host, params),
+        None => format!("{}:{}{}", scheme, host, params),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+
+    fn limits(total: usize, reserved_inbound: usize, host: &str) -> TrunkChannelLimits {
+        TrunkChannelLimits {
+            total,
+            reserved_inbound,
+            reserved_outbound: 0,
+            host: Some(host.to_string()),
+        }
+    }
+
+    #[test]
+    fn test_config_validation() {
+        let mut config = TrunkChannelConfig::default();
+        config.trunks.insert("carrier-a".to_string(), limits(30, 5, "a.example.com"));
+        assert!(config.validate().is_ok());
+
+        config.trunks.get_mut("carrier-a").unwrap().reserved_outbound = 26;
+        assert!(config.validate().is_err());
+        config.trunks.get_mut("carrier-a").unwrap().reserved_outbound = 0;
+
+        config.failover = vec!["carrier-b".to_string()];
+        assert!(config.validate().is_err());
+    }
+
+    #[test]
+    fn test_inbound_reservation_and_release() {
+        let mut config = TrunkChannelConfig::default();
+        config.trunks.insert("carrier-a".to_string(), limits(4, 2, "a.example.com"));
+        let manager = TrunkManager::new(config);
+
+        for i in 0..2 {
+            manager
+                .acquire(&format!("out-{}", i), "carrier-a", ChannelDirection::Outbound)
+                .unwrap();
+        }
+        // Outbound may not take the reserved channels
+        assert_eq!(
+            manager.acquire("out-2", "carrier-a", ChannelDirection::Outbound),
+            Err(TrunkFull {
+                trunk: "carrier-a".to_string(),
+                direction: ChannelDirection::Outbound,
+            })
+        );
+        manager.acquire("in-0", "carrier-a", ChannelDirection::Inbound).unwrap();
+        manager.acquire("in-1", "carrier-a", ChannelDirection::Inbound).unwrap();
+        assert!(manager.acquire("in-2", "carrier-a", ChannelDirection::Inbound).is_err());
+
+        // Releasing twice gives back one channel
+        manager.release("out-0");
+        manager.release("out-0");
+        let occupancy = manager.occupancy();
+        assert_eq!((occupancy[0].inbound, occupancy[0].outbound), (2, 1));
+        assert!(manager.acquire("out-2", "carrier-a", ChannelDirection::Outbound).is_ok());
+    }
+
+    #[test]
+    fn test_last_channel_race_does_not_over_admit() {
+        let mut config = TrunkChannelConfig::default();
+        config.trunks.insert("carrier-a".to_string(), limits(10, 0, "a.example.com"));
+        let manager = Arc::new(TrunkManager::new(config));
+
+        let handles: Vec<_> = (0..40)
+            .map(|i| {
+                let manager = manager.clone();
+                std::thread::spawn(move || {
+                    let direction = if i % 2 == 0 {
+                        ChannelDirection::Inbound
+                    } else {
+                        ChannelDirection::Outbound
+                    };
+                    manager.acquire(&format!("call-{}", i), "carrier-a", direction).is_ok()
+                })
+            })
+            .collect();
+        let admitted = handles
+            .into_iter()
+            .map(|handle| handle.join().unwrap())
+            .filter(|admitted| *admitted)
+            .count();
+        assert_eq!(admitted, 10);
+        let occupancy = &manager.occupancy()[0];
+        assert_eq!(occupancy.inbound + occupancy.outbound, 10);
+    }
+
+    #[test]
+    fn test_with_host() {
+        assert_eq!(
+            with_host("sip:+15551234567@a.example.com:5060;user=phone", "b.example.com"),
+            "sip:+15551234567@b.example.com;user=phone"
+        );
+        assert_eq!(with_host("sip:a.example.com", "b.example.com"), "sip:b.example.com");
+    }
+}
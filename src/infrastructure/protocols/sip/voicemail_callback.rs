@@ -0,0 +1,338 @@
+//! Voicemail callbacks over SIP
+//!
+//! A mailbox owner who presses "call back" after a message gets a call
+//! placed on their behalf to the sender, through the call router like any
+//! other outbound call. Once the sender answers, the call is established
+//! between the owner and the sender.
+
+use super::call_router::CallRouter;
+use super::message::{SipError, SipRequest};
+use super::redirect::{InviteForwarder, TrustZone};
+use crate::application::voicemail::VoicemailCallbackDialer;
+use crate::domain::call::EndReason;
+use async_trait::async_trait;
+use std::sync::Arc;
+use tracing::warn;
+use uuid::Uuid;
+
+/// Calls voicemail senders back through a [`CallRouter`]
+pub struct SipVoicemailCallback {
+    router: Arc<CallRouter>,
+    forwarder: Arc<dyn InviteForwarder>,
+    /// Domain of senders given as bare numbers
+    domain: String,
+}
+
+impl SipVoicemailCallback {
+    pub fn new(router: Arc<CallRouter>, forwarder: Arc<dyn InviteForwarder>, domain: &str) -> Self {
+        Self {
+            router,
+            forwarder,
+            domain: domain.to_string(),
+        }
+    }
+}
+
+/// INVITE calling `target` back on behalf of `owner_uri`
+fn callback_invite(call_id: &str, owner_uri: &str, target: &str) -> Result<SipRequest, SipError> {
+    let request = format!(
+        "INVITE {target} SIP/2.0\r\n\
+         Max-Forwards: 70\r\n\
+         From: <{owner}>;tag={tag}\r\n\
+         To: <{target}>\r\n\
+         Call-ID: {call_id}\r\n\
+         CSeq: 1 INVITE\r\n\
+         Content-Length: 0\r\n\r\n",
+        target = target,
+        owner = owner_uri,
+        tag = Uuid::new_v4().simple(),
+        call_id = call_id,
+    );
+    SipRequest::parse(request.as_bytes())
+}
+
+#[async_trait]
+impl VoicemailCallbackDialer for SipVoicemailCallback {
+    async fn call_back(&self, owner_uri: &str, number: &str) -> Result<String, String> {
+        let call_id = Uuid::new_v4().to_string();
+        let target = format!("sip:{}@{}", number, self.domain);
+        self.router
+            .create_call(call_id.clone(), owner_uri.to_string(), target.clone())
+            .await?;
+
+        let response = match callback_invite(&call_id, owner_uri, &target) {
+            Ok(invite) => {
+                self.router
+                    .forward_with_redirects(
+                        &call_id,
+                        &invite,
+                        &target,
+                        TrustZone::Internal,
+                        self.forwarder.as_ref(),
+                    )
+                    .await
+            }
+            Err(e) => Err(e),
+        };
+        let status = match response {
+            Ok(response) => response.status_code(),
+            Err(e) => {
+                warn!("Voicemail callback {} to {} failed: {}", call_id, target, e);
+                self.router.discard_call(&call_id).await;
+                return Err(e.to_string());
+            }
+        };
+        if !(200..300).contains(&status) {
+            let reason = match status {
+                486 | 600 => EndReason::Busy,
+                408 | 480 | 487 => EndReason::NoAnswer,
+                _ => EndReason::Rejected,
+            };
+            let _ = self.router.end_call(&call_id, reason).await;
+            return Err(format!("Rejected with {}", status));
+        }
+
+        self.router.answer_call(&call_id).await?;
+        Ok(call_id)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::application::voicemail::{RetrievalAudio, VoicemailRetrieval};
+    use crate::domain::audio::{WavFile, NARROWBAND_RATE};
+    use crate::domain::voicemail::{
+        VoicemailMailbox, VoicemailMessage, VoicemailRepository, VoicemailStatus,
+    };
+    use crate::domain::voicemail_ivr::{VoicemailIvrState, VoicemailPrompt};
+    use crate::domain::voicemail_service::VoicemailService;
+    use crate::infrastructure::persistence::MemoryVoicemailRepository;
+    use crate::infrastructure::protocols::sip::call_state::CallState;
+    use crate::infrastructure::protocols::sip::message::SipResponse;
+    use crate::infrastructure::protocols::sip::mwi_notifier::{MwiNotifier, MwiVoicemailRepository};
+    use crate::infrastructure::protocols::sip::registrar::Registrar;
+    use crate::infrastructure::protocols::sip::transport::OutgoingMessage;
+    use crate::test_support::{MockUa, Scenario};
+    use std::path::PathBuf;
+    use tokio::sync::mpsc;
+
+    struct Fixture {
+        registrar: Arc<Registrar>,
+        repository: Arc<MwiVoicemailRepository>,
+        notifies: mpsc::Receiver<OutgoingMessage>,
+        service: Arc<VoicemailService>,
+        dir: PathBuf,
+    }
+
+    /// Mailboxes 1001 and 1002 with their phones registered, and a message
+    /// of 800 samples of 1000 from 5550100 in 1001
+    async fn fixture() -> (Fixture, VoicemailMessage) {
+        let dir = std::env::temp_dir().join(format!("yakyak-vm-retrieval-{}", Uuid::new_v4()));
+        std::fs::create_dir_all(dir.join("1001")).unwrap();
+        std::fs::write(
+            dir.join("1001/msg.wav"),
+            WavFile::from_pcm(NARROWBAND_RATE, &[1000; 800]).to_wav_bytes(),
+        )
+        .unwrap();
+
+        let registrar = Arc::new(Registrar::new());
+        let store: Arc<dyn VoicemailRepository> = Arc::new(MemoryVoicemailRepository::new());
+        let (tx, notifies) = mpsc::channel(16);
+        let notifier = Arc::new(MwiNotifier::new(
+            registrar.clone(),
+            store.clone(),
+            tx,
+            "example.com".to_string(),
+            "192.0.2.1:5060".to_string(),
+        ));
+        let repository = Arc::new(MwiVoicemailRepository::new(store, notifier));
+        for (user, port) in [("1001", 5061), ("1002", 5062)] {
+            repository
+                .save_mailbox(VoicemailMailbox::new(user.to_string(), 1))
+                .await
+                .unwrap();
+            registrar
+                .add_binding(
+                    format!("sip:{}@example.com", user),
+                    format!("sip:{}@10.0.0.9:{}", user, port),
+                    3600,
+                )
+                .await
+                .unwrap();
+        }
+        let message = repository
+            .create_message(VoicemailMessage::new(
+                "1001".to_string(),
+                "sip:5550100@example.com".to_string(),
+                None,
+                1,
+                "1001/msg.wav".to_string(),
+                "wav".to_string(),
+            ))
+            .await
+            .unwrap();
+
+        let service = Arc::new(VoicemailService::new(&dir));
+        let fixture = Fixture {
+            registrar,
+            repository,
+            notifies,
+            service,
+            dir,
+        };
+        (fixture, message)
+    }
+
+    /// Last MWI NOTIFY body sent to `port`
+    fn last_summary(notifies: &mut mpsc::Receiver<OutgoingMessage>, port: u16) -> Option<String> {
+        let mut last = None;
+        while let Ok(message) = notifies.try_recv() {
+            if message.destination.port() == port {
+                let request = SipRequest::parse(&message.data).unwrap();
+                last = Some(String::from_utf8_lossy(request.body()).to_string());
+            }
+        }
+        last
+    }
+
+    fn prompt(prompt: VoicemailPrompt) -> RetrievalAudio {
+        prompt.into()
+    }
+
+    /// The sender's phone
+    struct Sender(MockUa);
+
+    #[async_trait]
+    impl InviteForwarder for Sender {
+        async fn forward(&self, target: &str, request: &SipRequest) -> Result<SipResponse, SipError> {
+            self.forward_with_progress(target, request, mpsc::unbounded_channel().0)
+                .await
+        }
+
+        async fn forward_with_progress(
+            &self,
+            target: &str,
+            request: &SipRequest,
+            progress: mpsc::UnboundedSender<u16>,
+        ) -> Result<SipResponse, SipError> {
+            if target == self.0.aor() {
+                self.0.forward_with_progress(target, request, progress).await
+            } else {
+                Err(SipError::TransportError(format!("{} unreachable", target)))
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_call_back_sender_after_envelope() {
+        let (mut fixture, message) = fixture().await;
+        let ua = MockUa::bind("sip:5550100@example.com").await.unwrap();
+        ua.set_scenario(Scenario::answer());
+        let router = Arc::new(CallRouter::new(fixture.registrar.clone()));
+        let dialer = SipVoicemailCallback::new(router.clone(), Arc::new(Sender(ua)), "example.com");
+        let mut retrieval = VoicemailRetrieval::new(
+            "1001",
+            "sip:1001@example.com",
+            fixture.repository.clone(),
+            fixture.service.clone(),
+        )
+        .with_dialer(Arc::new(dialer));
+
+        // Envelope, message, options; the message is now read
+        let audio = retrieval.start().await.unwrap();
+        let from = audio.iter().position(|a| a == &prompt(VoicemailPrompt::MessageFrom)).unwrap();
+        let number: Vec<_> = ["5", "5", "5", "0", "1", "0", "0"]
+            .iter()
+            .map(|d| RetrievalAudio::Prompt(format!("digit_{}", d)))
+            .collect();
+        assert_eq!(audio[from + 1..from + 8], number[..]);
+        assert_eq!(audio[from + 8], RetrievalAudio::Message("1001/msg.wav".to_string()));
+        assert_eq!(audio.last(), Some(&prompt(VoicemailPrompt::MessageActions)));
+        assert!(last_summary(&mut fixture.notifies, 5061)
+            .unwrap()
+            .contains("Messages-Waiting: no"));
+
+        assert_eq!(retrieval.press('5').await, vec![prompt(VoicemailPrompt::CallingBack)]);
+        assert!(retrieval.is_finished());
+
+        let calls = router.get_active_calls().await;
+        assert_eq!(calls.len(), 1);
+        assert_eq!(
+            router.get_call_state(&calls[0].call_id).await,
+            Some(CallState::Established)
+        );
+        let stored = fixture.repository.get_message(message.id).await.unwrap().unwrap();
+        assert_eq!(stored.status, VoicemailStatus::Saved);
+        assert!(last_summary(&mut fixture.notifies, 5061)
+            .unwrap()
+            .contains("Voice-Message: 0/1"));
+        std::fs::remove_dir_all(&fixture.dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_forward_with_comment_prepended() {
+        let (mut fixture, message) = fixture().await;
+        let mut retrieval = VoicemailRetrieval::new(
+            "1001",
+            "sip:1001@example.com",
+            fixture.repository.clone(),
+            fixture.service.clone(),
+        );
+        retrieval.start().await.unwrap();
+
+        // No such mailbox
+        assert_eq!(
+            retrieval.press('6').await,
+            vec![prompt(VoicemailPrompt::EnterForwardMailbox)]
+        );
+        for digit in ['9', '9', '#'] {
+            retrieval.press(digit).await;
+        }
+        assert_eq!(retrieval.state(), &VoicemailIvrState::MessageOptions);
+
+        retrieval.press('6').await;
+        for digit in ['1', '0', '0', '2'] {
+            assert!(retrieval.press(digit).await.is_empty());
+        }
+        assert_eq!(
+            retrieval.press('#').await,
+            vec![prompt(VoicemailPrompt::RecordForwardComment)]
+        );
+        retrieval.add_audio(&[-500; 400]);
+        assert_eq!(
+            retrieval.press('#').await,
+            vec![
+                prompt(VoicemailPrompt::MessageForwarded),
+                prompt(VoicemailPrompt::MessageActions),
+            ]
+        );
+
+        let forwarded = fixture.repository.list_messages("1002", None).await.unwrap();
+        assert_eq!(forwarded.len(), 1);
+        assert_eq!(forwarded[0].caller, message.caller);
+        assert_ne!(forwarded[0].audio_file_path, message.audio_file_path);
+        let samples = WavFile::from_file(fixture.dir.join(&forwarded[0].audio_file_path))
+            .unwrap()
+            .samples_i16();
+        assert_eq!(samples.len(), 1200);
+        assert!(samples[..400].iter().all(|&s| s == -500));
+        assert!(samples[400..].iter().all(|&s| s == 1000));
+        assert!(last_summary(&mut fixture.notifies, 5062)
+            .unwrap()
+            .contains("Voice-Message: 1/0"));
+
+        // Save the original to the old messages
+        assert_eq!(
+            retrieval.press('9').await,
+            vec![
+                prompt(VoicemailPrompt::MessageSaved),
+                prompt(VoicemailPrompt::NoMoreMessages),
+                prompt(VoicemailPrompt::MessageActions),
+            ]
+        );
+        let stored = fixture.repository.get_message(message.id).await.unwrap().unwrap();
+        assert_eq!(stored.status, VoicemailStatus::Saved);
+        std::fs::remove_dir_all(&fixture.dir).unwrap();
+    }
+}
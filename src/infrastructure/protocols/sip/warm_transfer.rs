@@ -0,0 +1,1118 @@
+//! Warm (consultative) transfer
+//!
+//! The transferor dials the consult code, the target's number and `#`
+//! during a call, or asks over the API: the transferee is put on hold with
+//! MOH and a consultation call goes out to the target. Once the target has
+//! answered, the transferor, on the consultation call, either completes the
+//! transfer (the target takes their place in the call, as with
+//! INVITE/Replaces), merges all three into an ad-hoc conference, or cancels
+//! and goes back to the transferee. A target that does not answer, or hangs
+//! up while consulted, returns the transferor to the transferee as well.
+//!
+//! The consultation call's CDR shares the correlation id of the call's;
+//! every change of a transfer is published as a [`TransferSession`].
+
+use super::call_router::CallRouter;
+use super::message::{SipError, SipRequest};
+use super::redirect::{InviteForwarder, TrustZone};
+use super::replaces::{LegReleaser, ReplacedLeg};
+use crate::application::events::EventBus;
+use crate::domain::call::EndReason;
+use crate::domain::conference::ParticipantRole;
+use crate::domain::conference_manager::ConferenceManager;
+use crate::domain::shared::value_objects::SipUri;
+use crate::infrastructure::ivr::{collect_digits, DtmfDigit, DtmfDispatcher, DtmfEvent};
+use chrono::{DateTime, Utc};
+use metrics::counter;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::sync::broadcast;
+use tokio::task::JoinHandle;
+use tracing::{debug, info, warn};
+use uuid::Uuid;
+
+/// Longest target number collected after the consult code
+const MAX_TARGET_DIGITS: usize = 32;
+
+/// DTMF codes and timeouts of warm transfers
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct WarmTransferConfig {
+    pub enabled: bool,
+    /// Dialed during a call, followed by the target's number and `#`
+    pub consult_code: String,
+    /// Dialed on the consultation call to hand the call to the target
+    pub complete_code: String,
+    /// Dialed on the consultation call to talk to both parties at once
+    pub merge_code: String,
+    /// Dialed on the consultation call to go back to the transferee
+    pub cancel_code: String,
+    /// Seconds the target has to answer
+    pub answer_timeout_secs: u64,
+    /// Seconds to dial the target's number in after the consult code
+    pub number_timeout_secs: u64,
+}
+
+impl Default for WarmTransferConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            consult_code: "*2".to_string(),
+            complete_code: "*4".to_string(),
+            merge_code: "*3".to_string(),
+            cancel_code: "*1".to_string(),
+            answer_timeout_secs: 30,
+            number_timeout_secs: 10,
+        }
+    }
+}
+
+impl WarmTransferConfig {
+    pub fn validate(&self) -> Result<(), String> {
+        let actions = [
+            ("complete_code", &self.complete_code),
+            ("merge_code", &self.merge_code),
+            ("cancel_code", &self.cancel_code),
+        ];
+        for (name, code) in [("consult_code", &self.consult_code)].iter().chain(&actions) {
+            if code.is_empty() || !code.chars().all(|c| DtmfDigit::from_char(c).is_some()) {
+                return Err(format!("{} must be DTMF digits", name));
+            }
+        }
+        if self.consult_code.contains('#') {
+            return Err("consult_code must not contain #, which ends the number".to_string());
+        }
+        for (name, code) in &actions {
+            for (other, other_code) in &actions {
+                if name != other && other_code.ends_with(code.as_str()) {
+                    return Err(format!("{} must not end with {}", other, name));
+                }
+            }
+        }
+        if self.answer_timeout_secs == 0 || self.answer_timeout_secs > 300 {
+            return Err("answer_timeout_secs must be between 1 and 300".to_string());
+        }
+        if self.number_timeout_secs == 0 || self.number_timeout_secs > 60 {
+            return Err("number_timeout_secs must be between 1 and 60".to_string());
+        }
+        Ok(())
+    }
+
+    pub fn answer_timeout(&self) -> Duration {
+        Duration::from_secs(self.answer_timeout_secs)
+    }
+
+    pub fn number_timeout(&self) -> Duration {
+        Duration::from_secs(self.number_timeout_secs)
+    }
+
+    /// The action whose code `dialed` ends with
+    fn action(&self, dialed: &str) -> Option<TransferAction> {
+        [
+            (&self.complete_code, TransferAction::Complete),
+            (&self.merge_code, TransferAction::Merge),
+            (&self.cancel_code, TransferAction::Cancel),
+        ]
+        .into_iter()
+        .find(|(code, _)| dialed.ends_with(code.as_str()))
+        .map(|(_, action)| action)
+    }
+
+    fn longest_code(&self) -> usize {
+        [
+            &self.consult_code,
+            &self.complete_code,
+            &self.merge_code,
+            &self.cancel_code,
+        ]
+        .iter()
+        .map(|code| code.len())
+        .max()
+        .unwrap_or(0)
+    }
+}
+
+/// How the transferor settles a consultation
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TransferAction {
+    /// The target takes the transferor's place
+    Complete,
+    /// All three talk in a conference
+    Merge,
+    /// The transferor goes back to the transferee
+    Cancel,
+}
+
+impl TransferAction {
+    pub fn parse(action: &str) -> Option<Self> {
+        match action {
+            "complete" => Some(Self::Complete),
+            "merge" => Some(Self::Merge),
+            "cancel" => Some(Self::Cancel),
+            _ => None,
+        }
+    }
+}
+
+/// Where a transfer stands
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TransferPhase {
+    /// The consultation call is ringing
+    Dialing,
+    /// The target answered; the transferee is on hold
+    Consulting,
+    /// The target took the transferor's place
+    Completed,
+    /// The three parties are in a conference
+    Merged,
+    /// The transferor went back to the transferee
+    Cancelled,
+    /// The target did not answer, or the call could not be handed over
+    Failed,
+    /// The transferee hung up; the consultation goes on as a call of its own
+    Abandoned,
+}
+
+impl TransferPhase {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Dialing => "dialing",
+            Self::Consulting => "consulting",
+            Self::Completed => "completed",
+            Self::Merged => "merged",
+            Self::Cancelled => "cancelled",
+            Self::Failed => "failed",
+            Self::Abandoned => "abandoned",
+        }
+    }
+
+    /// Whether the transfer is over
+    pub fn is_final(&self) -> bool {
+        !matches!(self, Self::Dialing | Self::Consulting)
+    }
+}
+
+/// A warm transfer, as published on each change
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TransferSession {
+    /// The call being transferred
+    pub call_id: String,
+    pub consult_call_id: String,
+    pub transferor_uri: String,
+    pub transferee_uri: String,
+    pub target: String,
+    pub phase: TransferPhase,
+    /// Why the transfer failed or was given up
+    pub reason: Option<String>,
+    /// Conference the parties were merged into
+    pub conference_id: Option<Uuid>,
+    pub started_at: DateTime<Utc>,
+}
+
+/// Warm transfer errors
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+pub enum WarmTransferError {
+    #[error("Call {0} not found")]
+    CallNotFound(String),
+    #[error("Call {0} is already being transferred")]
+    InProgress(String),
+    #[error("Call {0} has no transfer in progress")]
+    NotInProgress(String),
+    #[error("The transfer target of call {0} has not answered")]
+    NotAnswered(String),
+    #[error("Invalid transfer target {0}")]
+    InvalidTarget(String),
+    #[error("No conference bridge to merge call {0} into")]
+    NoConference(String),
+    #[error("No outbound transport to place consultation calls")]
+    NoForwarder,
+    #[error("{0}")]
+    Call(String),
+}
+
+/// INVITE of the consultation call from `transferor_uri` to `target`
+fn consult_invite(
+    call_id: &str,
+    transferor_uri: &str,
+    target: &str,
+) -> Result<SipRequest, SipError> {
+    let request = format!(
+        "INVITE {target} SIP/2.0\r\n\
+         Max-Forwards: 70\r\n\
+         From: <{transferor}>;tag={tag}\r\n\
+         To: <{target}>\r\n\
+         Call-ID: {call_id}\r\n\
+         CSeq: 1 INVITE\r\n\
+         Content-Length: 0\r\n\r\n",
+        target = target,
+        transferor = transferor_uri,
+        tag = Uuid::new_v4().simple(),
+        call_id = call_id,
+    );
+    SipRequest::parse(request.as_bytes())
+}
+
+/// Conference participant of the transferor of `call_id`, who is on both
+/// calls
+fn transferor_participant(call_id: &str) -> String {
+    format!("{};transferor", call_id)
+}
+
+/// Wait until `call_id` is gone from the router
+async fn ended(router: &CallRouter, call_id: &str) {
+    if let Some(mut state) = router.watch_call_state(call_id).await {
+        while state.changed().await.is_ok() {}
+    }
+}
+
+/// Runs warm transfers over a [`CallRouter`]
+pub struct WarmTransferManager {
+    router: Arc<CallRouter>,
+    config: WarmTransferConfig,
+    /// Domain of targets given as bare numbers
+    domain: String,
+    forwarder: Option<Arc<dyn InviteForwarder>>,
+    dtmf: Option<Arc<DtmfDispatcher>>,
+    conferences: Option<Arc<ConferenceManager>>,
+    releaser: Option<Arc<dyn LegReleaser>>,
+    /// Transfers in progress, by the Call-ID of the call transferred
+    sessions: Mutex<HashMap<String, TransferSession>>,
+    /// Calls whose DTMF is watched for transfer codes
+    watched: Mutex<HashSet<String>>,
+    events: broadcast::Sender<TransferSession>,
+}
+
+impl WarmTransferManager {
+    pub fn new(router: Arc<CallRouter>, config: WarmTransferConfig, domain: &str) -> Self {
+        let (events, _) = broadcast::channel(256);
+        Self {
+            router,
+            config,
+            domain: domain.to_string(),
+            forwarder: None,
+            dtmf: None,
+            conferences: None,
+            releaser: None,
+            sessions: Mutex::new(HashMap::new()),
+            watched: Mutex::new(HashSet::new()),
+            events,
+        }
+    }
+
+    /// Place consultation calls with `forwarder`; without one, transfers
+    /// are refused
+    pub fn with_forwarder(mut self, forwarder: Arc<dyn InviteForwarder>) -> Self {
+        self.forwarder = Some(forwarder);
+        self
+    }
+
+    /// Take transfer codes from `dtmf`
+    pub fn with_dtmf(mut self, dtmf: Arc<DtmfDispatcher>) -> Self {
+        self.dtmf = Some(dtmf);
+        self
+    }
+
+    /// Merge consultations into conferences of `conferences`
+    pub fn with_conferences(mut self, conferences: Arc<ConferenceManager>) -> Self {
+        self.conferences = Some(conferences);
+        self
+    }
+
+    /// Send the BYEs to transferors handed over and targets hung up
+    pub fn with_leg_releaser(mut self, releaser: Arc<dyn LegReleaser>) -> Self {
+        self.releaser = Some(releaser);
+        self
+    }
+
+    /// Transfer changes
+    pub fn subscribe(&self) -> broadcast::Receiver<TransferSession> {
+        self.events.subscribe()
+    }
+
+    /// The transfer in progress of a call, by the Call-ID of the call or of
+    /// its consultation
+    pub fn session(&self, call_id: &str) -> Option<TransferSession> {
+        self.sessions
+            .lock()
+            .unwrap()
+            .values()
+            .find(|session| session.call_id == call_id || session.consult_call_id == call_id)
+            .cloned()
+    }
+
+    fn uri(&self, number: &str) -> String {
+        if number.starts_with("sip:") || number.starts_with("sips:") {
+            number.to_string()
+        } else {
+            format!("sip:{}@{}", number, self.domain)
+        }
+    }
+
+    fn publish(&self, session: &TransferSession) {
+        if session.phase.is_final() {
+            counter!("sip_warm_transfers_total", "outcome" => session.phase.as_str()).increment(1);
+        }
+        let _ = self.events.send(session.clone());
+    }
+
+    /// Move the transfer of `call_id` on to `to` if it is in one of `from`;
+    /// a final phase ends it. The caller publishes the result.
+    fn advance(
+        &self,
+        call_id: &str,
+        from: &[TransferPhase],
+        to: TransferPhase,
+    ) -> Result<TransferSession, WarmTransferError> {
+        let mut sessions = self.sessions.lock().unwrap();
+        let key = sessions
+            .values()
+            .find(|session| session.call_id == call_id || session.consult_call_id == call_id)
+            .map(|session| session.call_id.clone())
+            .ok_or_else(|| WarmTransferError::NotInProgress(call_id.to_string()))?;
+        let Some(session) = sessions.get_mut(&key) else {
+            return Err(WarmTransferError::NotInProgress(key));
+        };
+        if !from.contains(&session.phase) {
+            return Err(match session.phase {
+                TransferPhase::Dialing => WarmTransferError::NotAnswered(key),
+                _ => WarmTransferError::NotInProgress(key),
+            });
+        }
+        session.phase = to;
+        let session = session.clone();
+        if to.is_final() {
+            sessions.remove(&key);
+        }
+        Ok(session)
+    }
+
+    /// Put `call_id` on hold and call `target` for the transferor to
+    /// consult
+    ///
+    /// The transferor is the party at `transferor_uri`, by default the
+    /// callee. The consultation call is placed in the background.
+    pub async fn consult(
+        self: &Arc<Self>,
+        call_id: &str,
+        target: &str,
+        transferor_uri: Option<&str>,
+    ) -> Result<TransferSession, WarmTransferError> {
+        let forwarder = self.forwarder.clone().ok_or(WarmTransferError::NoForwarder)?;
+        let call_id = self.router.canonical_call_id(call_id).await;
+        if self.router.get_call_state(&call_id).await.is_none() {
+            return Err(WarmTransferError::CallNotFound(call_id));
+        }
+        if self.session(&call_id).is_some() {
+            return Err(WarmTransferError::InProgress(call_id));
+        }
+        let target = self.uri(target);
+        if let Err(e) = SipUri::parse(&target) {
+            return Err(WarmTransferError::InvalidTarget(format!("{}: {}", target, e)));
+        }
+
+        let consult_call_id = Uuid::new_v4().to_string();
+        let (transferor_uri, transferee_uri) = self
+            .router
+            .begin_consultation(&call_id, &consult_call_id, transferor_uri)
+            .await
+            .map_err(WarmTransferError::Call)?;
+        if let Err(e) = self
+            .router
+            .create_call(consult_call_id.clone(), transferor_uri.clone(), target.clone())
+            .await
+        {
+            let _ = self.router.end_consultation(&call_id).await;
+            return Err(WarmTransferError::Call(e));
+        }
+        self.router.link_calls(&call_id, &consult_call_id).await;
+
+        let session = TransferSession {
+            call_id: call_id.clone(),
+            consult_call_id,
+            transferor_uri,
+            transferee_uri,
+            target,
+            phase: TransferPhase::Dialing,
+            reason: None,
+            conference_id: None,
+            started_at: Utc::now(),
+        };
+        self.sessions
+            .lock()
+            .unwrap()
+            .insert(call_id.clone(), session.clone());
+        self.publish(&session);
+        info!(
+            "{} consulting {} about call {}",
+            session.transferor_uri, session.target, call_id
+        );
+
+        let manager = self.clone();
+        let dialing = session.clone();
+        tokio::spawn(async move { manager.dial(dialing, forwarder).await });
+        Ok(session)
+    }
+
+    /// Place the consultation call of `session` and go on from its answer
+    async fn dial(self: Arc<Self>, session: TransferSession, forwarder: Arc<dyn InviteForwarder>) {
+        let consult_id = session.consult_call_id.as_str();
+        let status = match consult_invite(consult_id, &session.transferor_uri, &session.target) {
+            Ok(invite) => {
+                let forward = self.router.forward_with_redirects(
+                    consult_id,
+                    &invite,
+                    &session.target,
+                    TrustZone::Internal,
+                    forwarder.as_ref(),
+                );
+                match tokio::time::timeout(self.config.answer_timeout(), forward).await {
+                    Ok(Ok(response)) => response.status_code(),
+                    Ok(Err(e)) => {
+                        warn!("Consultation call {} to {} failed: {}", consult_id, session.target, e);
+                        503
+                    }
+                    Err(_) => 408,
+                }
+            }
+            Err(e) => {
+                warn!("Failed to build consultation INVITE to {}: {}", session.target, e);
+                500
+            }
+        };
+
+        if !(200..300).contains(&status) {
+            let reason = match status {
+                486 | 600 => EndReason::Busy,
+                408 | 480 | 487 => EndReason::NoAnswer,
+                _ => EndReason::Rejected,
+            };
+            let _ = self.router.end_call(consult_id, reason).await;
+            // A transfer cancelled meanwhile has its transferee back already
+            if let Ok(mut failed) =
+                self.advance(&session.call_id, &[TransferPhase::Dialing], TransferPhase::Failed)
+            {
+                if let Err(e) = self.router.end_consultation(&failed.call_id).await {
+                    warn!("Failed to resume transferee of call {}: {}", failed.call_id, e);
+                }
+                warn!(
+                    "Transfer target {} of call {} answered {}",
+                    failed.target, failed.call_id, status
+                );
+                failed.reason = Some(format!("Target answered {}", status));
+                self.publish(&failed);
+            }
+            return;
+        }
+
+        if let Err(e) = self.router.answer_call(consult_id).await {
+            warn!("Failed to answer consultation call {}: {}", consult_id, e);
+        }
+        match self.advance(
+            &session.call_id,
+            &[TransferPhase::Dialing],
+            TransferPhase::Consulting,
+        ) {
+            Ok(consulting) => {
+                self.watch(consult_id);
+                self.publish(&consulting);
+                self.supervise(consulting);
+            }
+            // Cancelled before the target answered
+            Err(_) => self.hang_up(consult_id).await,
+        }
+    }
+
+    /// Cancel the transfer when the target hangs up while consulted, and
+    /// give it up when the transferee does
+    fn supervise(self: &Arc<Self>, session: TransferSession) {
+        let manager = self.clone();
+        tokio::spawn(async move {
+            let router = manager.router.clone();
+            tokio::select! {
+                _ = ended(&router, &session.consult_call_id) => {
+                    if let Ok(mut cancelled) = manager.advance(
+                        &session.call_id,
+                        &[TransferPhase::Consulting],
+                        TransferPhase::Cancelled,
+                    ) {
+                        if let Err(e) = router.end_consultation(&cancelled.call_id).await {
+                            warn!("Failed to resume transferee of call {}: {}", cancelled.call_id, e);
+                        }
+                        cancelled.reason = Some("Target hung up".to_string());
+                        manager.publish(&cancelled);
+                    }
+                }
+                _ = ended(&router, &session.call_id) => {
+                    if let Ok(mut abandoned) = manager.advance(
+                        &session.call_id,
+                        &[TransferPhase::Consulting],
+                        TransferPhase::Abandoned,
+                    ) {
+                        abandoned.reason = Some("Transferee hung up".to_string());
+                        manager.publish(&abandoned);
+                    }
+                }
+            }
+        });
+    }
+
+    /// End a consultation call, with a BYE to the target
+    async fn hang_up(&self, consult_id: &str) {
+        let contact = self.router.get_callee_contact(consult_id).await;
+        let target = self
+            .router
+            .get_active_call(consult_id)
+            .await
+            .map(|call| call.callee_uri);
+        if self
+            .router
+            .end_call(consult_id, EndReason::NormalClearing)
+            .await
+            .is_err()
+        {
+            return;
+        }
+        if let (Some(releaser), Some(uri)) = (&self.releaser, target) {
+            let leg = ReplacedLeg {
+                uri,
+                contact,
+                confirmed: true,
+            };
+            if let Err(e) = releaser.release(consult_id, &leg).await {
+                warn!("Failed to hang up consultation call {}: {}", consult_id, e);
+            }
+        }
+    }
+
+    /// Settle the transfer of `call_id` with `action`
+    pub async fn apply(
+        &self,
+        call_id: &str,
+        action: TransferAction,
+    ) -> Result<TransferSession, WarmTransferError> {
+        match action {
+            TransferAction::Complete => self.complete(call_id).await,
+            TransferAction::Merge => self.merge(call_id).await,
+            TransferAction::Cancel => self.cancel(call_id).await,
+        }
+    }
+
+    /// Hand the call over to the target, who takes the transferor's place;
+    /// the transferor's leg is released
+    pub async fn complete(&self, call_id: &str) -> Result<TransferSession, WarmTransferError> {
+        let mut session =
+            self.advance(call_id, &[TransferPhase::Consulting], TransferPhase::Completed)?;
+        let result = self
+            .router
+            .splice_consultation(&session.call_id, &session.consult_call_id)
+            .await;
+        match result {
+            Ok(transferor) => {
+                if let Some(releaser) = &self.releaser {
+                    if let Err(e) = releaser.release(&session.call_id, &transferor).await {
+                        warn!(
+                            "Failed to release transferor {} of call {}: {}",
+                            transferor.uri, session.call_id, e
+                        );
+                    }
+                }
+                info!("Call {} transferred to {}", session.call_id, session.target);
+                self.publish(&session);
+                Ok(session)
+            }
+            Err(e) => {
+                warn!("Failed to complete transfer of call {}: {}", session.call_id, e);
+                // The transferee is not left on hold
+                let _ = self.router.end_consultation(&session.call_id).await;
+                session.phase = TransferPhase::Failed;
+                session.reason = Some(e.clone());
+                self.publish(&session);
+                Err(WarmTransferError::Call(e))
+            }
+        }
+    }
+
+    /// Bring the transferee, the transferor and the target together in an
+    /// ad-hoc conference
+    pub async fn merge(&self, call_id: &str) -> Result<TransferSession, WarmTransferError> {
+        let conferences = self
+            .conferences
+            .clone()
+            .ok_or_else(|| WarmTransferError::NoConference(call_id.to_string()))?;
+        let mut session =
+            self.advance(call_id, &[TransferPhase::Consulting], TransferPhase::Merged)?;
+        match self.mix(&conferences, &session).await {
+            Ok(room_id) => {
+                session.conference_id = Some(room_id);
+                info!("Call {} merged with {} in conference {}", session.call_id, session.target, room_id);
+                self.leave_when_ended(conferences, &session);
+                self.publish(&session);
+                Ok(session)
+            }
+            Err(e) => {
+                warn!("Failed to merge call {}: {}", session.call_id, e);
+                let _ = self.router.end_consultation(&session.call_id).await;
+                session.phase = TransferPhase::Failed;
+                session.reason = Some(e.clone());
+                self.publish(&session);
+                Err(WarmTransferError::Call(e))
+            }
+        }
+    }
+
+    /// Join the parties of `session` to a new conference, whose mixer then
+    /// carries the media of both calls
+    async fn mix(&self, conferences: &ConferenceManager, session: &TransferSession) -> Result<Uuid, String> {
+        self.router.end_consultation(&session.call_id).await?;
+        let room_id = conferences
+            .create_room(format!("Transfer of {}", session.call_id), None, 3)
+            .await?;
+        let parties = [
+            (session.call_id.clone(), &session.transferee_uri, ParticipantRole::Attendee),
+            (
+                transferor_participant(&session.call_id),
+                &session.transferor_uri,
+                ParticipantRole::Moderator,
+            ),
+            (session.consult_call_id.clone(), &session.target, ParticipantRole::Attendee),
+        ];
+        for (participant, uri, role) in parties {
+            let name = CallRouter::extract_username(uri);
+            conferences
+                .join_conference(room_id, participant, name, role, None)
+                .await?;
+        }
+        self.router.detach_media(&session.call_id).await;
+        self.router.detach_media(&session.consult_call_id).await;
+        Ok(room_id)
+    }
+
+    /// Take the parties of a merged transfer out of the conference as their
+    /// calls end; the transferor leaves with the last one
+    fn leave_when_ended(&self, conferences: Arc<ConferenceManager>, session: &TransferSession) {
+        let router = self.router.clone();
+        let (call_id, consult_id) = (session.call_id.clone(), session.consult_call_id.clone());
+        tokio::spawn(async move {
+            tokio::join!(
+                async {
+                    ended(&router, &call_id).await;
+                    let _ = conferences.leave_conference(&call_id).await;
+                },
+                async {
+                    ended(&router, &consult_id).await;
+                    let _ = conferences.leave_conference(&consult_id).await;
+                },
+            );
+            let _ = conferences
+                .leave_conference(&transferor_participant(&call_id))
+                .await;
+        });
+    }
+
+    /// Drop the consultation and take the transferee off hold
+    pub async fn cancel(&self, call_id: &str) -> Result<TransferSession, WarmTransferError> {
+        let session = self.advance(
+            call_id,
+            &[TransferPhase::Dialing, TransferPhase::Consulting],
+            TransferPhase::Cancelled,
+        )?;
+        // A target still ringing is hung up once it answers
+        let answered = self
+            .router
+            .get_call_state(&session.consult_call_id)
+            .await
+            .is_some_and(|state| state.is_established());
+        if answered {
+            self.hang_up(&session.consult_call_id).await;
+        }
+        if let Err(e) = self.router.end_consultation(&session.call_id).await {
+            warn!("Failed to resume transferee of call {}: {}", session.call_id, e);
+        }
+        info!("Transfer of call {} to {} cancelled", session.call_id, session.target);
+        self.publish(&session);
+        Ok(session)
+    }
+
+    /// Act on the transfer codes dialed on `call_id` until it ends
+    ///
+    /// Each call is watched once, however often this is called.
+    pub fn watch(self: &Arc<Self>, call_id: &str) {
+        let Some(dtmf) = &self.dtmf else {
+            return;
+        };
+        if !self.watched.lock().unwrap().insert(call_id.to_string()) {
+            return;
+        }
+        let mut digits = dtmf.subscribe(call_id);
+        let manager = self.clone();
+        let call_id = call_id.to_string();
+        tokio::spawn(async move {
+            let longest = manager.config.longest_code();
+            let mut dialed = String::new();
+            loop {
+                match digits.recv().await {
+                    Ok(event) => dialed.push(event.digit.to_char()),
+                    Err(broadcast::error::RecvError::Lagged(_)) => continue,
+                    Err(broadcast::error::RecvError::Closed) => break,
+                }
+                if dialed.len() > longest {
+                    dialed.remove(0);
+                }
+                if manager.dialed(&call_id, &dialed, &mut digits).await {
+                    dialed.clear();
+                }
+            }
+            manager.watched.lock().unwrap().remove(&call_id);
+        });
+    }
+
+    /// Act on the code `dialed` ends with; true when there was one
+    async fn dialed(
+        self: &Arc<Self>,
+        call_id: &str,
+        dialed: &str,
+        digits: &mut broadcast::Receiver<DtmfEvent>,
+    ) -> bool {
+        match self.session(call_id) {
+            // Codes on the consultation call settle its transfer
+            Some(session) if session.consult_call_id == call_id => {
+                let Some(action) = self.config.action(dialed) else {
+                    return false;
+                };
+                if let Err(e) = self.apply(call_id, action).await {
+                    warn!("Transfer code {} on call {} refused: {}", dialed, call_id, e);
+                }
+                true
+            }
+            Some(_) => false,
+            None if dialed.ends_with(self.config.consult_code.as_str()) => {
+                let number =
+                    collect_digits(digits, MAX_TARGET_DIGITS, self.config.number_timeout()).await;
+                match number {
+                    Some(number) => {
+                        if let Err(e) = self.consult(call_id, &number, None).await {
+                            warn!("Transfer of call {} to {} refused: {}", call_id, number, e);
+                        }
+                    }
+                    None => debug!("No transfer target dialed on call {}", call_id),
+                }
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Watch the DTMF of every answered call for transfer codes
+    pub fn spawn(self: &Arc<Self>, bus: &dyn EventBus) -> JoinHandle<()> {
+        let mut events = bus.subscribe();
+        let manager = self.clone();
+        tokio::spawn(async move {
+            loop {
+                match events.recv().await {
+                    Ok(envelope) if envelope.event_type() == "call.answered" => {
+                        manager.watch(&envelope.call_id)
+                    }
+                    Ok(_) => {}
+                    Err(broadcast::error::RecvError::Lagged(skipped)) => {
+                        warn!("Missed {} call events for warm transfers", skipped);
+                    }
+                    Err(broadcast::error::RecvError::Closed) => break,
+                }
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::infrastructure::protocols::sip::message::SipResponse;
+    use crate::infrastructure::protocols::sip::registrar::Registrar;
+    use crate::test_support::{MockUa, Scenario};
+    use async_trait::async_trait;
+    use tokio::sync::mpsc;
+
+    /// Mock UAS endpoints by AOR
+    struct Endpoints(HashMap<String, MockUa>);
+
+    #[async_trait]
+    impl InviteForwarder for Endpoints {
+        async fn forward(&self, target: &str, request: &SipRequest) -> Result<SipResponse, SipError> {
+            self.forward_with_progress(target, request, mpsc::unbounded_channel().0)
+                .await
+        }
+
+        async fn forward_with_progress(
+            &self,
+            target: &str,
+            request: &SipRequest,
+            progress: mpsc::UnboundedSender<u16>,
+        ) -> Result<SipResponse, SipError> {
+            match self.0.get(target) {
+                Some(ua) => ua.forward_with_progress(target, request, progress).await,
+                None => Err(SipError::TransportError(format!("{} unreachable", target))),
+            }
+        }
+    }
+
+    /// BYEs sent, as (Call-ID, party)
+    #[derive(Default)]
+    struct Released(Mutex<Vec<(String, String)>>);
+
+    #[async_trait]
+    impl LegReleaser for Released {
+        async fn release(&self, call_id: &str, leg: &ReplacedLeg) -> Result<(), SipError> {
+            self.0
+                .lock()
+                .unwrap()
+                .push((call_id.to_string(), leg.uri.clone()));
+            Ok(())
+        }
+    }
+
+    struct Setup {
+        router: Arc<CallRouter>,
+        manager: Arc<WarmTransferManager>,
+        dtmf: Arc<DtmfDispatcher>,
+        conferences: Arc<ConferenceManager>,
+        released: Arc<Released>,
+        events: broadcast::Receiver<TransferSession>,
+    }
+
+    /// Alice's call `call_id` to bob, answered, and targets 2001 (answers)
+    /// and 2002 (busy)
+    async fn setup(call_id: &str) -> Setup {
+        let mut endpoints = HashMap::new();
+        for (user, scenario) in [
+            ("2001", Scenario::ring_then_answer(Duration::from_millis(20))),
+            ("2002", Scenario::reject(486)),
+        ] {
+            let ua = MockUa::bind(&format!("sip:{}@example.com", user)).await.unwrap();
+            ua.set_scenario(scenario);
+            endpoints.insert(ua.aor().to_string(), ua);
+        }
+        let router = Arc::new(CallRouter::new(Arc::new(Registrar::new())));
+        router
+            .create_call(
+                call_id.to_string(),
+                "sip:alice@example.com".to_string(),
+                "sip:bob@example.com".to_string(),
+            )
+            .await
+            .unwrap();
+        router.answer_call(call_id).await.unwrap();
+
+        let dtmf = Arc::new(DtmfDispatcher::new());
+        let conferences = Arc::new(ConferenceManager::new());
+        let released = Arc::new(Released::default());
+        let config = WarmTransferConfig {
+            enabled: true,
+            ..Default::default()
+        };
+        let manager = Arc::new(
+            WarmTransferManager::new(router.clone(), config, "example.com")
+                .with_forwarder(Arc::new(Endpoints(endpoints)))
+                .with_dtmf(dtmf.clone())
+                .with_conferences(conferences.clone())
+                .with_leg_releaser(released.clone()),
+        );
+        let events = manager.subscribe();
+        Setup {
+            router,
+            manager,
+            dtmf,
+            conferences,
+            released,
+            events,
+        }
+    }
+
+    async fn reached(
+        events: &mut broadcast::Receiver<TransferSession>,
+        phase: TransferPhase,
+    ) -> TransferSession {
+        let wait = async {
+            loop {
+                let session = events.recv().await.unwrap();
+                if session.phase == phase {
+                    return session;
+                }
+            }
+        };
+        tokio::time::timeout(Duration::from_secs(5), wait)
+            .await
+            .unwrap_or_else(|_| panic!("transfer did not reach {:?}", phase))
+    }
+
+    fn dial(dtmf: &DtmfDispatcher, call_id: &str, digits: &str) {
+        for digit in digits.chars() {
+            let digit = DtmfDigit::from_char(digit).unwrap();
+            dtmf.publish(call_id, DtmfEvent::new(digit, Duration::from_millis(100)));
+        }
+    }
+
+    #[test]
+    fn test_config_validation() {
+        assert!(WarmTransferConfig::default().validate().is_ok());
+        let config = WarmTransferConfig {
+            merge_code: "1*4".to_string(),
+            ..Default::default()
+        };
+        assert!(config.validate().unwrap_err().contains("merge_code"));
+        let config = WarmTransferConfig {
+            consult_code: "*2#".to_string(),
+            ..Default::default()
+        };
+        assert!(config.validate().is_err());
+        let config = WarmTransferConfig {
+            cancel_code: "*x".to_string(),
+            ..Default::default()
+        };
+        assert!(config.validate().is_err());
+    }
+
+    #[tokio::test]
+    async fn test_complete_by_dtmf() {
+        let Setup {
+            router,
+            manager,
+            dtmf,
+            released,
+            mut events,
+            ..
+        } = setup("call-complete").await;
+        manager.watch("call-complete");
+        tokio::time::sleep(Duration::from_millis(10)).await;
+
+        // Bob dials *2, the target and #; alice waits on hold
+        dial(&dtmf, "call-complete", "*22001#");
+        let session = reached(&mut events, TransferPhase::Dialing).await;
+        assert_eq!(session.transferor_uri, "sip:bob@example.com");
+        assert_eq!(session.transferee_uri, "sip:alice@example.com");
+        assert_eq!(session.target, "sip:2001@example.com");
+        assert!(router.is_call_on_hold("call-complete").await);
+        assert!(router.moh_source("call-complete").await.is_some());
+        // The hold has no reminders of its own
+        assert!(router.held_calls().await.is_empty());
+
+        let session = reached(&mut events, TransferPhase::Consulting).await;
+        assert!(router.get_active_call(&session.consult_call_id).await.is_some());
+        dial(&dtmf, &session.consult_call_id, "*4");
+        reached(&mut events, TransferPhase::Completed).await;
+
+        // Alice talks to the target; bob is released
+        let call = router.get_active_call("call-complete").await.unwrap();
+        assert_eq!(call.caller_uri, "sip:alice@example.com");
+        assert_eq!(call.callee_uri, "sip:2001@example.com");
+        assert!(!call.on_hold);
+        assert!(router.get_active_call(&session.consult_call_id).await.is_none());
+        assert_eq!(router.active_call_count().await, 1);
+        assert_eq!(
+            router.canonical_call_id(&session.consult_call_id).await,
+            "call-complete"
+        );
+        assert_eq!(
+            *released.0.lock().unwrap(),
+            [("call-complete".to_string(), "sip:bob@example.com".to_string())]
+        );
+        assert!(manager.session("call-complete").is_none());
+    }
+
+    #[tokio::test]
+    async fn test_merge_into_conference() {
+        let Setup {
+            router,
+            manager,
+            conferences,
+            mut events,
+            ..
+        } = setup("call-merge").await;
+
+        let session = manager
+            .consult("call-merge", "2001", Some("sip:bob@example.com"))
+            .await
+            .unwrap();
+        assert!(matches!(
+            manager.complete("call-merge").await,
+            Err(WarmTransferError::NotAnswered(_))
+        ));
+        reached(&mut events, TransferPhase::Consulting).await;
+        let merged = manager.apply("call-merge", TransferAction::Merge).await.unwrap();
+        assert_eq!(merged.phase, TransferPhase::Merged);
+        assert_eq!(reached(&mut events, TransferPhase::Merged).await.conference_id, merged.conference_id);
+
+        // Both calls go on, off hold, mixed in a conference of three
+        let room_id = merged.conference_id.unwrap();
+        assert_eq!(conferences.participant_count(room_id).await.unwrap(), 3);
+        for participant in [
+            "call-merge".to_string(),
+            session.consult_call_id.clone(),
+            transferor_participant("call-merge"),
+        ] {
+            assert_eq!(conferences.get_conference_for_call(&participant).await, Some(room_id));
+        }
+        assert!(!router.is_call_on_hold("call-merge").await);
+        assert_eq!(router.active_call_count().await, 2);
+        let call = router.get_active_call("call-merge").await.unwrap();
+        assert_eq!(call.callee_uri, "sip:bob@example.com");
+
+        // The transferee hanging up leaves the conference
+        router.terminate_call("call-merge").await.unwrap();
+        for _ in 0..100 {
+            if conferences.participant_count(room_id).await.unwrap_or(0) == 2 {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(5)).await;
+        }
+        assert!(!conferences.is_in_conference("call-merge").await);
+    }
+
+    #[tokio::test]
+    async fn test_cancel_and_unanswered_target() {
+        let Setup {
+            router,
+            manager,
+            dtmf,
+            released,
+            mut events,
+            ..
+        } = setup("call-cancel").await;
+
+        let session = manager.consult("call-cancel", "2001", None).await.unwrap();
+        assert!(matches!(
+            manager.consult("call-cancel", "2002", None).await,
+            Err(WarmTransferError::InProgress(_))
+        ));
+        reached(&mut events, TransferPhase::Consulting).await;
+        manager.watch(&session.consult_call_id);
+        tokio::time::sleep(Duration::from_millis(10)).await;
+        dial(&dtmf, &session.consult_call_id, "*1");
+        reached(&mut events, TransferPhase::Cancelled).await;
+
+        // Bob is back with alice; the target got a BYE
+        let call = router.get_active_call("call-cancel").await.unwrap();
+        assert_eq!(call.callee_uri, "sip:bob@example.com");
+        assert!(!call.on_hold);
+        assert!(router.get_active_call(&session.consult_call_id).await.is_none());
+        assert_eq!(
+            *released.0.lock().unwrap(),
+            [(session.consult_call_id.clone(), "sip:2001@example.com".to_string())]
+        );
+
+        // A busy target returns bob to alice too
+        let session = manager.consult("call-cancel", "2002", None).await.unwrap();
+        let failed = reached(&mut events, TransferPhase::Failed).await;
+        assert_eq!(failed.reason.as_deref(), Some("Target answered 486"));
+        assert!(!router.is_call_on_hold("call-cancel").await);
+        assert!(router.get_active_call(&session.consult_call_id).await.is_none());
+        assert_eq!(router.active_call_count().await, 1);
+        assert!(matches!(
+            manager.cancel("call-cancel").await,
+            Err(WarmTransferError::NotInProgress(_))
+        ));
+    }
+}
@@ -0,0 +1,629 @@
+//! In-call metadata channel of browser softphones
+//!
+//! A browser that negotiated a data channel m-line opens one reliable,
+//! ordered data channel. The server pushes call updates on it as JSON
+//! text messages (caller identity, hold and resume, transfer progress,
+//! quality warnings); the browser only acknowledges them or pings.
+//!
+//! [`DataChannelSession`] is the server end of the SCTP association and
+//! does no I/O: packets received over the call's DTLS transport go into
+//! [`handle_packet`](DataChannelSession::handle_packet), and packets from
+//! [`take_outgoing`](DataChannelSession::take_outgoing) are sent back over
+//! it. The association is always set up by the browser.
+
+use super::dcep::{
+    DcepError, DcepMessage, CHANNEL_RELIABLE, PPID_DCEP, PPID_STRING, PPID_STRING_EMPTY,
+};
+use super::sctp::{Chunk, DataChunk, InitChunk, SctpError, SctpPacket};
+use super::sdp::{DEFAULT_MAX_MESSAGE_SIZE, DEFAULT_SCTP_PORT};
+use crate::domain::call::CallEvent;
+use crate::infrastructure::transcription::{CaptionEvent, CaptionLeg};
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+use tracing::{debug, warn};
+
+/// Receive window we advertise
+const RECEIVE_WINDOW: u32 = 131_072;
+/// Streams we offer in each direction
+const STREAMS: u16 = 1024;
+
+/// Update pushed to the browser
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ServerMessage {
+    /// Who is calling, once resolved
+    CallerInfo {
+        call_id: String,
+        uri: String,
+        display_name: Option<String>,
+    },
+    Hold { call_id: String },
+    Resume { call_id: String },
+    /// Progress of a transfer, from the transferee's NOTIFYs
+    TransferProgress {
+        call_id: String,
+        target: String,
+        status: u16,
+        reason: String,
+        /// Set once the transfer succeeded or failed
+        done: bool,
+    },
+    /// A media quality metric crossed its threshold
+    QualityWarning {
+        call_id: String,
+        metric: String,
+        value: f64,
+        threshold: f64,
+    },
+    /// Caption text of a live transcribed call; interim until `is_final`
+    Caption {
+        call_id: String,
+        leg: CaptionLeg,
+        text: String,
+        is_final: bool,
+        start_ms: u64,
+        end_ms: u64,
+    },
+    /// Answer to a ping
+    Pong { id: u64 },
+}
+
+impl ServerMessage {
+    /// Message mirroring `event`, for the call events shown during a call
+    pub fn from_call_event(event: &CallEvent) -> Option<Self> {
+        let call_id = event.call_id().as_uuid().to_string();
+        match event {
+            CallEvent::Initiated(initiated) => Some(ServerMessage::CallerInfo {
+                call_id,
+                uri: initiated.caller.uri().to_string(),
+                display_name: initiated.caller.display_name().map(str::to_string),
+            }),
+            CallEvent::Held(_) => Some(ServerMessage::Hold { call_id }),
+            CallEvent::Resumed(_) => Some(ServerMessage::Resume { call_id }),
+            _ => None,
+        }
+    }
+
+    /// Message carrying a caption to the browser in the call
+    pub fn from_caption(caption: &CaptionEvent) -> Self {
+        ServerMessage::Caption {
+            call_id: caption.call_id.clone(),
+            leg: caption.leg,
+            text: caption.text.clone(),
+            is_final: caption.is_final,
+            start_ms: caption.start_ms,
+            end_ms: caption.end_ms,
+        }
+    }
+}
+
+/// A server message with the number the browser acknowledges it by
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ServerEnvelope {
+    pub seq: u64,
+    #[serde(flatten)]
+    pub message: ServerMessage,
+}
+
+/// What the browser may send
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ClientMessage {
+    /// Server message `seq` was handled
+    Ack { seq: u64 },
+    /// Debug ping, answered with a pong
+    Ping { id: u64 },
+}
+
+/// What happened on the association
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ChannelEvent {
+    /// The browser set up the SCTP association
+    Established,
+    /// The browser opened the data channel
+    Opened { label: String, protocol: String },
+    /// The browser acknowledged server message `seq`
+    Acknowledged { seq: u64 },
+    /// The association was aborted or shut down
+    Closed,
+}
+
+/// Data channel errors
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+pub enum DataChannelError {
+    #[error(transparent)]
+    Sctp(#[from] SctpError),
+    #[error(transparent)]
+    Dcep(#[from] DcepError),
+    #[error("Verification tag mismatch")]
+    WrongTag,
+    #[error("Unknown state cookie")]
+    BadCookie,
+    #[error("No open data channel")]
+    NotOpen,
+    #[error("Message of {0} bytes exceeds the maximum size")]
+    TooLarge(usize),
+}
+
+/// The open data channel
+#[derive(Debug, Clone)]
+struct Channel {
+    stream_id: u16,
+    /// Stream sequence number of our next ordered message
+    next_ssn: u16,
+}
+
+/// Server end of a data channel association
+pub struct DataChannelSession {
+    local_port: u16,
+    remote_port: u16,
+    /// Tag the browser puts on its packets
+    local_tag: u32,
+    /// Tag we put on ours
+    peer_tag: u32,
+    cookie: Option<Vec<u8>>,
+    established: bool,
+    /// TSN of our next DATA chunk
+    next_tsn: u32,
+    /// Highest TSN received with nothing missing below it
+    peer_cumulative_tsn: u32,
+    channel: Option<Channel>,
+    /// Fragments of a message being received
+    partial: Vec<u8>,
+    /// DATA chunks sent and not acknowledged yet, oldest first
+    in_flight: VecDeque<DataChunk>,
+    next_seq: u64,
+    outgoing: Vec<Vec<u8>>,
+}
+
+impl DataChannelSession {
+    pub fn new() -> Self {
+        let mut rng = rand::thread_rng();
+        Self {
+            local_port: DEFAULT_SCTP_PORT,
+            remote_port: DEFAULT_SCTP_PORT,
+            local_tag: rng.gen_range(1..=u32::MAX),
+            peer_tag: 0,
+            cookie: None,
+            established: false,
+            next_tsn: rng.gen(),
+            peer_cumulative_tsn: 0,
+            channel: None,
+            partial: Vec::new(),
+            in_flight: VecDeque::new(),
+            next_seq: 0,
+            outgoing: Vec::new(),
+        }
+    }
+
+    /// Whether the browser opened the data channel
+    pub fn is_open(&self) -> bool {
+        self.established && self.channel.is_some()
+    }
+
+    /// Packets to send over DTLS
+    pub fn take_outgoing(&mut self) -> Vec<Vec<u8>> {
+        std::mem::take(&mut self.outgoing)
+    }
+
+    /// Handle an SCTP packet received over DTLS
+    pub fn handle_packet(&mut self, data: &[u8]) -> Result<Vec<ChannelEvent>, DataChannelError> {
+        let packet = SctpPacket::decode(data)?;
+        if let Some(Chunk::Init(init)) = packet.chunks.first() {
+            self.accept_init(packet.source_port, *init);
+            return Ok(Vec::new());
+        }
+        if packet.verification_tag != self.local_tag {
+            return Err(DataChannelError::WrongTag);
+        }
+
+        let mut events = Vec::new();
+        let mut control = Vec::new();
+        let mut data_chunks = Vec::new();
+        let mut acknowledge = false;
+        for chunk in packet.chunks {
+            match chunk {
+                Chunk::CookieEcho(cookie) => {
+                    if self.cookie.as_ref() != Some(&cookie) {
+                        return Err(DataChannelError::BadCookie);
+                    }
+                    if !self.established {
+                        self.established = true;
+                        events.push(ChannelEvent::Established);
+                    }
+                    control.push(Chunk::CookieAck);
+                }
+                Chunk::Data(data) if self.established => {
+                    acknowledge = true;
+                    if data.tsn != self.peer_cumulative_tsn.wrapping_add(1) {
+                        // Duplicate, or out of order: the browser retransmits
+                        // what we do not acknowledge
+                        continue;
+                    }
+                    self.peer_cumulative_tsn = data.tsn;
+                    if let Some(event) = self.receive(data, &mut data_chunks) {
+                        events.push(event);
+                    }
+                }
+                Chunk::Sack { cumulative_tsn, .. } => {
+                    while self
+                        .in_flight
+                        .front()
+                        .is_some_and(|chunk| tsn_at_most(chunk.tsn, cumulative_tsn))
+                    {
+                        self.in_flight.pop_front();
+                    }
+                }
+                Chunk::Heartbeat(info) => control.push(Chunk::HeartbeatAck(info)),
+                Chunk::Abort | Chunk::ShutdownComplete => {
+                    self.close();
+                    events.push(ChannelEvent::Closed);
+                }
+                Chunk::Shutdown { .. } => {
+                    control.push(Chunk::ShutdownAck);
+                    self.close();
+                    events.push(ChannelEvent::Closed);
+                }
+                other => debug!("Ignoring SCTP chunk {:?}", other),
+            }
+        }
+        if acknowledge {
+            control.push(Chunk::Sack {
+                cumulative_tsn: self.peer_cumulative_tsn,
+                a_rwnd: RECEIVE_WINDOW,
+            });
+        }
+        self.transmit(control.into_iter().chain(data_chunks.into_iter().map(Chunk::Data)));
+        Ok(events)
+    }
+
+    /// Push `message` to the browser; returns the sequence number it
+    /// acknowledges it by
+    pub fn send(&mut self, message: ServerMessage) -> Result<u64, DataChannelError> {
+        if !self.established {
+            return Err(DataChannelError::NotOpen);
+        }
+        let seq = self.next_seq;
+        let payload = serde_json::to_vec(&ServerEnvelope { seq, message })
+            .expect("server messages serialize");
+        if payload.len() > DEFAULT_MAX_MESSAGE_SIZE as usize {
+            return Err(DataChannelError::TooLarge(payload.len()));
+        }
+        let chunk = self
+            .channel_chunk(PPID_STRING, payload)
+            .ok_or(DataChannelError::NotOpen)?;
+        self.next_seq += 1;
+        self.transmit(std::iter::once(Chunk::Data(chunk)));
+        Ok(seq)
+    }
+
+    /// Send again what the browser has not acknowledged, when the
+    /// retransmission timer fires
+    pub fn retransmit(&mut self) {
+        let chunks: Vec<Chunk> = self.in_flight.iter().cloned().map(Chunk::Data).collect();
+        for chunk in chunks {
+            self.outgoing.push(
+                SctpPacket::new(self.local_port, self.remote_port, self.peer_tag)
+                    .with_chunk(chunk)
+                    .encode(),
+            );
+        }
+    }
+
+    fn accept_init(&mut self, source_port: u16, init: InitChunk) {
+        self.remote_port = source_port;
+        self.peer_tag = init.initiate_tag;
+        self.peer_cumulative_tsn = init.initial_tsn.wrapping_sub(1);
+        let cookie: [u8; 16] = rand::thread_rng().gen();
+        self.cookie = Some(cookie.to_vec());
+        let init_ack = Chunk::InitAck {
+            init: InitChunk {
+                initiate_tag: self.local_tag,
+                a_rwnd: RECEIVE_WINDOW,
+                outbound_streams: STREAMS,
+                inbound_streams: STREAMS,
+                initial_tsn: self.next_tsn,
+            },
+            cookie: cookie.to_vec(),
+        };
+        self.transmit(std::iter::once(init_ack));
+    }
+
+    /// Reassemble a message; DCEP replies go into `replies`
+    fn receive(&mut self, data: DataChunk, replies: &mut Vec<DataChunk>) -> Option<ChannelEvent> {
+        if data.beginning {
+            self.partial.clear();
+        }
+        self.partial.extend_from_slice(&data.payload);
+        if !data.ending {
+            return None;
+        }
+        let message = std::mem::take(&mut self.partial);
+
+        match data.ppid {
+            PPID_DCEP => match DcepMessage::decode(&message) {
+                Ok(DcepMessage::Open(open)) => {
+                    if open.channel_type != CHANNEL_RELIABLE || self.channel.is_some() {
+                        warn!(
+                            "Refusing data channel {:?} (type {}): only one reliable ordered channel is supported",
+                            open.label, open.channel_type
+                        );
+                        return None;
+                    }
+                    self.channel = Some(Channel {
+                        stream_id: data.stream_id,
+                        next_ssn: 0,
+                    });
+                    replies.extend(self.channel_chunk(PPID_DCEP, DcepMessage::Ack.encode()));
+                    Some(ChannelEvent::Opened {
+                        label: open.label,
+                        protocol: open.protocol,
+                    })
+                }
+                Ok(DcepMessage::Ack) => None,
+                Err(e) => {
+                    warn!("Invalid DCEP message: {}", e);
+                    None
+                }
+            },
+            PPID_STRING | PPID_STRING_EMPTY => match serde_json::from_slice(&message) {
+                Ok(ClientMessage::Ack { seq }) => Some(ChannelEvent::Acknowledged { seq }),
+                Ok(ClientMessage::Ping { id }) => {
+                    let pong = ServerEnvelope {
+                        seq: self.next_seq,
+                        message: ServerMessage::Pong { id },
+                    };
+                    self.next_seq += 1;
+                    let payload = serde_json::to_vec(&pong).expect("server messages serialize");
+                    replies.extend(self.channel_chunk(PPID_STRING, payload));
+                    None
+                }
+                Err(e) => {
+                    warn!("Invalid data channel message: {}", e);
+                    None
+                }
+            },
+            ppid => {
+                debug!("Ignoring data channel message with PPID {}", ppid);
+                None
+            }
+        }
+    }
+
+    /// Ordered DATA chunk on the open channel, tracked until acknowledged
+    fn channel_chunk(&mut self, ppid: u32, payload: Vec<u8>) -> Option<DataChunk> {
+        let channel = self.channel.as_mut()?;
+        let chunk = DataChunk {
+            tsn: self.next_tsn,
+            stream_id: channel.stream_id,
+            ssn: channel.next_ssn,
+            ppid,
+            unordered: false,
+            beginning: true,
+            ending: true,
+            payload,
+        };
+        channel.next_ssn = channel.next_ssn.wrapping_add(1);
+        self.next_tsn = self.next_tsn.wrapping_add(1);
+        self.in_flight.push_back(chunk.clone());
+        Some(chunk)
+    }
+
+    fn transmit(&mut self, chunks: impl Iterator<Item = Chunk>) {
+        let mut packet = SctpPacket::new(self.local_port, self.remote_port, self.peer_tag);
+        packet.chunks.extend(chunks);
+        if !packet.chunks.is_empty() {
+            self.outgoing.push(packet.encode());
+        }
+    }
+
+    fn close(&mut self) {
+        self.established = false;
+        self.channel = None;
+        self.cookie = None;
+        self.in_flight.clear();
+    }
+}
+
+impl Default for DataChannelSession {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Whether `tsn` is at or before `cumulative` in serial number order
+fn tsn_at_most(tsn: u32, cumulative: u32) -> bool {
+    cumulative.wrapping_sub(tsn) < 1 << 31
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::infrastructure::protocols::webrtc::dcep::ChannelOpen;
+
+    /// Browser end of the association, scripted
+    struct ScriptedPeer {
+        tag: u32,
+        server_tag: u32,
+        next_tsn: u32,
+        next_ssn: u16,
+    }
+
+    impl ScriptedPeer {
+        fn new() -> Self {
+            Self {
+                tag: 0x0BAD_CAFE,
+                server_tag: 0,
+                next_tsn: 1000,
+                next_ssn: 0,
+            }
+        }
+
+        fn packet(&self, chunks: Vec<Chunk>) -> Vec<u8> {
+            let mut packet = SctpPacket::new(5000, 5000, self.server_tag);
+            packet.chunks = chunks;
+            packet.encode()
+        }
+
+        fn data(&mut self, ppid: u32, payload: Vec<u8>) -> Chunk {
+            let chunk = DataChunk {
+                tsn: self.next_tsn,
+                stream_id: 0,
+                ssn: self.next_ssn,
+                ppid,
+                unordered: false,
+                beginning: true,
+                ending: true,
+                payload,
+            };
+            self.next_tsn += 1;
+            self.next_ssn += 1;
+            Chunk::Data(chunk)
+        }
+
+        /// INIT, COOKIE ECHO and DATA_CHANNEL_OPEN
+        fn open(&mut self, session: &mut DataChannelSession) -> Vec<ChannelEvent> {
+            let init = SctpPacket::new(5000, 5000, 0)
+                .with_chunk(Chunk::Init(InitChunk {
+                    initiate_tag: self.tag,
+                    a_rwnd: 131_072,
+                    outbound_streams: 1024,
+                    inbound_streams: 1024,
+                    initial_tsn: self.next_tsn,
+                }))
+                .encode();
+            assert!(session.handle_packet(&init).unwrap().is_empty());
+            let init_ack = received(session).remove(0);
+            assert_eq!(init_ack.verification_tag, self.tag);
+            let Chunk::InitAck { init, cookie } = &init_ack.chunks[0] else {
+                panic!("expected INIT ACK, got {:?}", init_ack.chunks);
+            };
+            self.server_tag = init.initiate_tag;
+
+            let mut events = session
+                .handle_packet(&self.packet(vec![Chunk::CookieEcho(cookie.clone())]))
+                .unwrap();
+            assert_eq!(received(session)[0].chunks, vec![Chunk::CookieAck]);
+
+            let open = self.data(
+                PPID_DCEP,
+                DcepMessage::Open(ChannelOpen::reliable("call-events", "yakyak.v1")).encode(),
+            );
+            events.extend(session.handle_packet(&self.packet(vec![open])).unwrap());
+            events
+        }
+    }
+
+    fn received(session: &mut DataChannelSession) -> Vec<SctpPacket> {
+        session
+            .take_outgoing()
+            .iter()
+            .map(|bytes| SctpPacket::decode(bytes).unwrap())
+            .collect()
+    }
+
+    fn data_chunks(packets: &[SctpPacket]) -> Vec<DataChunk> {
+        packets
+            .iter()
+            .flat_map(|packet| packet.chunks.iter())
+            .filter_map(|chunk| match chunk {
+                Chunk::Data(data) => Some(data.clone()),
+                _ => None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_dcep_open_handshake() {
+        let mut session = DataChannelSession::new();
+        let mut peer = ScriptedPeer::new();
+        let events = peer.open(&mut session);
+        assert_eq!(
+            events,
+            vec![
+                ChannelEvent::Established,
+                ChannelEvent::Opened {
+                    label: "call-events".to_string(),
+                    protocol: "yakyak.v1".to_string(),
+                },
+            ]
+        );
+        assert!(session.is_open());
+
+        // The OPEN is acknowledged at the SCTP level and answered with a
+        // DATA_CHANNEL_ACK on the same stream
+        let packets = received(&mut session);
+        assert!(packets[0].chunks.contains(&Chunk::Sack {
+            cumulative_tsn: 1000,
+            a_rwnd: RECEIVE_WINDOW
+        }));
+        let ack = &data_chunks(&packets)[0];
+        assert_eq!((ack.ppid, ack.stream_id), (PPID_DCEP, 0));
+        assert_eq!(DcepMessage::decode(&ack.payload), Ok(DcepMessage::Ack));
+
+        // Packets with another verification tag are refused
+        let stray = SctpPacket::new(5000, 5000, 1).with_chunk(Chunk::CookieAck).encode();
+        assert_eq!(session.handle_packet(&stray), Err(DataChannelError::WrongTag));
+    }
+
+    #[test]
+    fn test_message_round_trip() {
+        let mut session = DataChannelSession::new();
+        assert_eq!(
+            session.send(ServerMessage::Hold {
+                call_id: "call-1".to_string()
+            }),
+            Err(DataChannelError::NotOpen)
+        );
+        let mut peer = ScriptedPeer::new();
+        peer.open(&mut session);
+        let dcep_ack = data_chunks(&received(&mut session)).remove(0);
+
+        let seq = session
+            .send(ServerMessage::Hold {
+                call_id: "call-1".to_string(),
+            })
+            .unwrap();
+        let hold = data_chunks(&received(&mut session)).remove(0);
+        assert_eq!(hold.ppid, PPID_STRING);
+        assert_eq!(hold.tsn, dcep_ack.tsn.wrapping_add(1));
+        let envelope: ServerEnvelope = serde_json::from_slice(&hold.payload).unwrap();
+        assert_eq!(
+            envelope,
+            ServerEnvelope {
+                seq,
+                message: ServerMessage::Hold {
+                    call_id: "call-1".to_string()
+                },
+            }
+        );
+
+        // Unacknowledged chunks are retransmitted until the SACK
+        session.retransmit();
+        assert_eq!(data_chunks(&received(&mut session)).len(), 2);
+
+        let ack = peer.data(
+            PPID_STRING,
+            serde_json::to_vec(&ClientMessage::Ack { seq }).unwrap(),
+        );
+        let sack = Chunk::Sack {
+            cumulative_tsn: hold.tsn,
+            a_rwnd: 131_072,
+        };
+        let events = session
+            .handle_packet(&peer.packet(vec![sack, ack]))
+            .unwrap();
+        assert_eq!(events, vec![ChannelEvent::Acknowledged { seq }]);
+        received(&mut session);
+        session.retransmit();
+        assert!(session.take_outgoing().is_empty());
+
+        // Debug ping
+        let ping = peer.data(PPID_STRING, br#"{"type":"ping","id":7}"#.to_vec());
+        session.handle_packet(&peer.packet(vec![ping])).unwrap();
+        let pong = data_chunks(&received(&mut session)).remove(0);
+        let pong: ServerEnvelope = serde_json::from_slice(&pong.payload).unwrap();
+        assert_eq!(pong.message, ServerMessage::Pong { id: 7 });
+    }
+}
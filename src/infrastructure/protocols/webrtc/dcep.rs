@@ -0,0 +1,127 @@
+//! Data Channel Establishment Protocol (RFC 8832)
+//!
+//! The side opening a data channel sends DATA_CHANNEL_OPEN on the stream
+//! it picked; the other side answers DATA_CHANNEL_ACK on the same stream,
+//! and both may send user messages from then on.
+
+/// Payload protocol identifiers of data channel messages (RFC 8831
+/// section 8)
+pub const PPID_DCEP: u32 = 50;
+pub const PPID_STRING: u32 = 51;
+pub const PPID_BINARY: u32 = 53;
+pub const PPID_STRING_EMPTY: u32 = 56;
+pub const PPID_BINARY_EMPTY: u32 = 57;
+
+const MESSAGE_ACK: u8 = 0x02;
+const MESSAGE_OPEN: u8 = 0x03;
+
+/// Reliable, ordered delivery
+pub const CHANNEL_RELIABLE: u8 = 0x00;
+
+/// DCEP errors
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+pub enum DcepError {
+    #[error("Message too short")]
+    MessageTooShort,
+    #[error("Unknown message type: {0}")]
+    UnknownMessageType(u8),
+    #[error("Label or protocol is not UTF-8")]
+    InvalidText,
+}
+
+/// DATA_CHANNEL_OPEN
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ChannelOpen {
+    /// Reliability and ordering, `CHANNEL_RELIABLE` for the default
+    pub channel_type: u8,
+    pub priority: u16,
+    /// Retransmissions or lifetime of partially reliable channels
+    pub reliability: u32,
+    pub label: String,
+    /// Subprotocol, empty when none
+    pub protocol: String,
+}
+
+impl ChannelOpen {
+    /// Reliable ordered channel
+    pub fn reliable(label: &str, protocol: &str) -> Self {
+        Self {
+            channel_type: CHANNEL_RELIABLE,
+            priority: 0,
+            reliability: 0,
+            label: label.to_string(),
+            protocol: protocol.to_string(),
+        }
+    }
+}
+
+/// DCEP message
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DcepMessage {
+    Open(ChannelOpen),
+    Ack,
+}
+
+impl DcepMessage {
+    pub fn encode(&self) -> Vec<u8> {
+        match self {
+            DcepMessage::Ack => vec![MESSAGE_ACK],
+            DcepMessage::Open(open) => {
+                let mut out = Vec::with_capacity(12 + open.label.len() + open.protocol.len());
+                out.push(MESSAGE_OPEN);
+                out.push(open.channel_type);
+                out.extend_from_slice(&open.priority.to_be_bytes());
+                out.extend_from_slice(&open.reliability.to_be_bytes());
+                out.extend_from_slice(&(open.label.len() as u16).to_be_bytes());
+                out.extend_from_slice(&(open.protocol.len() as u16).to_be_bytes());
+                out.extend_from_slice(open.label.as_bytes());
+                out.extend_from_slice(open.protocol.as_bytes());
+                out
+            }
+        }
+    }
+
+    pub fn decode(data: &[u8]) -> Result<Self, DcepError> {
+        match data.first() {
+            None => Err(DcepError::MessageTooShort),
+            Some(&MESSAGE_ACK) => Ok(DcepMessage::Ack),
+            Some(&MESSAGE_OPEN) => {
+                if data.len() < 12 {
+                    return Err(DcepError::MessageTooShort);
+                }
+                let label_len = u16::from_be_bytes([data[8], data[9]]) as usize;
+                let protocol_len = u16::from_be_bytes([data[10], data[11]]) as usize;
+                if data.len() < 12 + label_len + protocol_len {
+                    return Err(DcepError::MessageTooShort);
+                }
+                let text = |bytes: &[u8]| {
+                    String::from_utf8(bytes.to_vec()).map_err(|_| DcepError::InvalidText)
+                };
+                Ok(DcepMessage::Open(ChannelOpen {
+                    channel_type: data[1],
+                    priority: u16::from_be_bytes([data[2], data[3]]),
+                    reliability: u32::from_be_bytes([data[4], data[5], data[6], data[7]]),
+                    label: text(&data[12..12 + label_len])?,
+                    protocol: text(&data[12 + label_len..12 + label_len + protocol_len])?,
+                }))
+            }
+            Some(other) => Err(DcepError::UnknownMessageType(*other)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_open_roundtrip() {
+        let open = DcepMessage::Open(ChannelOpen::reliable("call-events", "yakyak.v1"));
+        let bytes = open.encode();
+        assert_eq!(bytes[0], 0x03);
+        assert_eq!(bytes.len(), 12 + "call-events".len() + "yakyak.v1".len());
+        assert_eq!(DcepMessage::decode(&bytes), Ok(open));
+        assert_eq!(DcepMessage::decode(&[0x02]), Ok(DcepMessage::Ack));
+        assert_eq!(DcepMessage::decode(&bytes[..10]), Err(DcepError::MessageTooShort));
+    }
+}
@@ -1,7 +1,18 @@
 //! WebRTC protocol implementation
 pub mod sdp;
+#[cfg(feature = "datachannel")]
+pub mod datachannel;
+#[cfg(feature = "datachannel")]
+pub mod dcep;
+#[cfg(feature = "datachannel")]
+pub mod sctp;
 
 pub use sdp::{
     WebRtcSdp, SdpType, MediaDescription, MediaType, MediaDirection,
     RtpCodec, DtlsFingerprint, DtlsSetup, create_audio_offer,
 };
+#[cfg(feature = "datachannel")]
+pub use datachannel::{
+    ChannelEvent, ClientMessage, DataChannelError, DataChannelSession, ServerEnvelope,
+    ServerMessage,
+};
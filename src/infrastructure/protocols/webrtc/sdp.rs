@@ -1,8 +1,18 @@
 /// WebRTC SDP (Session Description Protocol) support
+use crate::infrastructure::media::rtp::{ExtMap, AUDIO_LEVEL_URI};
 use crate::infrastructure::protocols::ice::candidate::IceCandidate;
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
 
+/// Format of data channel m-lines (RFC 8841)
+pub const DATA_CHANNEL_FORMAT: &str = "webrtc-datachannel";
+
+/// SCTP port of the data channel association (`a=sctp-port`)
+pub const DEFAULT_SCTP_PORT: u16 = 5000;
+
+/// Largest data channel message we accept (`a=max-message-size`)
+pub const DEFAULT_MAX_MESSAGE_SIZE: u32 = 65536;
+
 /// SDP session type
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
 pub enum SdpType {
@@ -66,6 +76,15 @@ impl MediaType {
             MediaType::Application => "application",
         }
     }
+
+    pub fn from_string(s: &str) -> Option<Self> {
+        match s {
+            "audio" => Some(MediaType::Audio),
+            "video" => Some(MediaType::Video),
+            "application" => Some(MediaType::Application),
+            _ => None,
+        }
+    }
 }
 
 /// RTP codec
@@ -140,6 +159,12 @@ pub struct MediaDescription {
     pub dtls_setup: Option<DtlsSetup>,
     pub rtcp_mux: bool,
     pub mid: Option<String>,
+    /// RTP header extensions (a=extmap)
+    pub extmaps: Vec<ExtMap>,
+    /// SCTP port of a data channel m-line (a=sctp-port)
+    pub sctp_port: Option<u16>,
+    /// Largest message a data channel peer accepts (a=max-message-size)
+    pub max_message_size: Option<u32>,
 }
 
 impl MediaDescription {
@@ -157,7 +182,42 @@ impl MediaDescription {
             dtls_setup: None,
             rtcp_mux: true,
             mid: None,
+            extmaps: Vec::new(),
+            sctp_port: None,
+            max_message_size: None,
+        }
+    }
+
+    /// Data channel m-line (`m=application 9 UDP/DTLS/SCTP
+    /// webrtc-datachannel`)
+    pub fn data_channel(port: u16) -> Self {
+        Self {
+            protocol: "UDP/DTLS/SCTP".to_string(),
+            rtcp_mux: false,
+            sctp_port: Some(DEFAULT_SCTP_PORT),
+            max_message_size: Some(DEFAULT_MAX_MESSAGE_SIZE),
+            ..Self::new(MediaType::Application, port)
+        }
+    }
+
+    /// Whether this is a data channel m-line
+    pub fn is_data_channel(&self) -> bool {
+        self.media_type == MediaType::Application && self.protocol.ends_with("SCTP")
+    }
+
+    /// Answer to an offered data channel m-line
+    ///
+    /// Without the `datachannel` feature the m-line is rejected with port
+    /// 0 and the client falls back to the WebSocket event stream.
+    pub fn answer_data_channel(offer: &MediaDescription) -> Self {
+        let port = if cfg!(feature = "datachannel") { 9 } else { 0 };
+        let mut answer = Self::data_channel(port);
+        answer.mid = offer.mid.clone();
+        if port == 0 {
+            answer.sctp_port = None;
+            answer.max_message_size = None;
         }
+        answer
     }
 
     /// Add codec
@@ -165,6 +225,11 @@ impl MediaDescription {
         self.codecs.push(codec);
     }
 
+    /// Local ID of the header extension `uri`, if negotiated
+    pub fn extmap_id(&self, uri: &str) -> Option<u8> {
+        self.extmaps.iter().find(|e| e.uri == uri).map(|e| e.id)
+    }
+
     /// Set ICE credentials
     pub fn set_ice_credentials(&mut self, ufrag: String, pwd: String) {
         self.ice_ufrag = Some(ufrag);
@@ -318,10 +383,14 @@ impl WebRtcSdp {
     /// Add media description to SDP string
     fn add_media_to_sdp(&self, sdp: &mut String, media: &MediaDescription) {
         // m= line
-        let payload_types: Vec<String> = media.codecs
-            .iter()
-            .map(|c| c.payload_type.to_string())
-            .collect();
+        let payload_types: Vec<String> = if media.is_data_channel() {
+            vec![DATA_CHANNEL_FORMAT.to_string()]
+        } else {
+            media.codecs
+                .iter()
+                .map(|c| c.payload_type.to_string())
+                .collect()
+        };
 
         sdp.push_str(&format!(
             "m={} {} {} {}\r\n",
@@ -344,8 +413,10 @@ impl WebRtcSdp {
             sdp.push_str(&format!("a=mid:{}\r\n", mid));
         }
 
-        // Direction
-        sdp.push_str(&format!("a={}\r\n", media.direction.to_string()));
+        // Direction (data channels have none)
+        if !media.is_data_channel() {
+            sdp.push_str(&format!("a={}\r\n", media.direction.to_string()));
+        }
 
         // ICE credentials
         if let Some(ref ufrag) = media.ice_ufrag {
@@ -363,6 +434,19 @@ impl WebRtcSdp {
             sdp.push_str(&format!("a=setup:{}\r\n", setup.to_string()));
         }
 
+        // SCTP association of data channels
+        if let Some(port) = media.sctp_port {
+            sdp.push_str(&format!("a=sctp-port:{}\r\n", port));
+        }
+        if let Some(size) = media.max_message_size {
+            sdp.push_str(&format!("a=max-message-size:{}\r\n", size));
+        }
+
+        // Header extensions
+        for extmap in &media.extmaps {
+            sdp.push_str(&format!("a={}\r\n", extmap.to_attribute()));
+        }
+
         // Codecs (rtpmap)
         for codec in &media.codecs {
             sdp.push_str(&format!("a=rtpmap:{}\r\n", codec.to_rtpmap()));
@@ -391,6 +475,31 @@ impl WebRtcSdp {
         for line in sdp.lines() {
             if line.starts_with("s=") {
                 webrtc_sdp.session_name = line[2..].to_string();
+            } else if let Some(m_line) = line.strip_prefix("m=") {
+                let mut parts = m_line.split_whitespace();
+                let media_type = parts.next().and_then(MediaType::from_string);
+                let port = parts.next().and_then(|p| p.parse().ok());
+                if let (Some(media_type), Some(port)) = (media_type, port) {
+                    let mut media = MediaDescription::new(media_type, port);
+                    // Only when the m-line says so
+                    media.rtcp_mux = false;
+                    if let Some(protocol) = parts.next() {
+                        media.protocol = protocol.to_string();
+                    }
+                    webrtc_sdp.add_media(media);
+                }
+            } else if let Some(media) = webrtc_sdp.media_descriptions.last_mut() {
+                if let Some(mid) = line.strip_prefix("a=mid:") {
+                    media.mid = Some(mid.trim().to_string());
+                } else if line.trim_end() == "a=rtcp-mux" {
+                    media.rtcp_mux = true;
+                } else if let Some(port) = line.strip_prefix("a=sctp-port:") {
+                    media.sctp_port = port.trim().parse().ok();
+                } else if let Some(size) = line.strip_prefix("a=max-message-size:") {
+                    media.max_message_size = size.trim().parse().ok();
+                } else if let Some(extmap) = line.strip_prefix("a=").and_then(ExtMap::parse) {
+                    media.extmaps.push(extmap);
+                }
             }
             // Add more parsing as needed
         }
@@ -407,6 +516,9 @@ pub fn create_audio_offer(ice_ufrag: String, ice_pwd: String) -> WebRtcSdp {
     audio.mid = Some("0".to_string());
     audio.set_ice_credentials(ice_ufrag, ice_pwd);
 
+    // Browsers report their audio level to the mixer (RFC 6464)
+    audio.extmaps.push(ExtMap::new(1, AUDIO_LEVEL_URI));
+
     // Add codecs
     audio.add_codec(RtpCodec::opus());
     audio.add_codec(RtpCodec::pcmu());
@@ -488,6 +600,7 @@ mod tests {
         assert_eq!(audio.media_type, MediaType::Audio);
         assert_eq!(audio.ice_ufrag, Some("ufrag123".to_string()));
         assert_eq!(audio.codecs.len(), 3); // Opus, PCMU, PCMA
+        assert_eq!(audio.extmap_id(AUDIO_LEVEL_URI), Some(1));
     }
 
     #[test]
@@ -501,6 +614,58 @@ mod tests {
         assert!(sdp_string.contains("a=ice-pwd:pwd456"));
         assert!(sdp_string.contains("a=rtpmap:111 opus/48000/2"));
         assert!(sdp_string.contains("a=group:BUNDLE"));
+        // Always offered toward browsers
+        assert!(sdp_string.contains("a=rtcp-mux\r\n"));
+        assert!(sdp_string.contains("a=extmap:1 urn:ietf:params:rtp-hdrext:ssrc-audio-level\r\n"));
+    }
+
+    #[test]
+    fn test_parse_browser_extmaps() {
+        let sdp = "v=0\r\n\
+                   o=- 4611731400430051336 2 IN IP4 127.0.0.1\r\n\
+                   s=-\r\n\
+                   t=0 0\r\n\
+                   a=extmap-allow-mixed\r\n\
+                   m=audio 9 UDP/TLS/RTP/SAVPF 111 63 9 0 8\r\n\
+                   a=mid:0\r\n\
+                   a=extmap:1 urn:ietf:params:rtp-hdrext:ssrc-audio-level\r\n\
+                   a=extmap:2 http://www.webrtc.org/experiments/rtp-hdrext/abs-send-time\r\n\
+                   a=extmap:3 http://www.ietf.org/id/draft-holmer-rmcat-transport-wide-cc-extensions-01\r\n\
+                   a=extmap:4 urn:ietf:params:rtp-hdrext:sdes:mid\r\n\
+                   a=rtpmap:111 opus/48000/2\r\n";
+        let offer = WebRtcSdp::from_sdp_string(sdp, SdpType::Offer).unwrap();
+        assert_eq!(offer.media_descriptions.len(), 1);
+        let audio = &offer.media_descriptions[0];
+        assert_eq!(audio.mid.as_deref(), Some("0"));
+        assert!(!audio.rtcp_mux);
+        assert_eq!(audio.extmaps.len(), 4);
+        assert_eq!(audio.extmap_id(AUDIO_LEVEL_URI), Some(1));
+        assert_eq!(audio.extmap_id("urn:ietf:params:rtp-hdrext:sdes:mid"), Some(4));
+    }
+
+    #[test]
+    fn test_data_channel_m_line() {
+        let sdp = "v=0\r\n\
+                   m=application 9 UDP/DTLS/SCTP webrtc-datachannel\r\n\
+                   a=mid:1\r\n\
+                   a=sctp-port:5000\r\n\
+                   a=max-message-size:262144\r\n";
+        let offer = WebRtcSdp::from_sdp_string(sdp, SdpType::Offer).unwrap();
+        let data = &offer.media_descriptions[0];
+        assert!(data.is_data_channel());
+        assert_eq!((data.sctp_port, data.max_message_size), (Some(5000), Some(262144)));
+
+        let mut answer = WebRtcSdp::new(SdpType::Answer);
+        answer.add_media(MediaDescription::answer_data_channel(data));
+        let answer = answer.to_sdp_string();
+        if cfg!(feature = "datachannel") {
+            assert!(answer.contains("m=application 9 UDP/DTLS/SCTP webrtc-datachannel\r\n"));
+            assert!(answer.contains("a=sctp-port:5000\r\n"));
+        } else {
+            assert!(answer.contains("m=application 0 UDP/DTLS/SCTP webrtc-datachannel\r\n"));
+        }
+        assert!(answer.contains("a=mid:1\r\n"));
+        assert!(!answer.contains("a=sendrecv"));
     }
 
     #[test]
@@ -0,0 +1,70 @@
+//! Hot standby replication
+//!
+//! The active node streams registrations and active-call metadata to a
+//! standby peer over TLS. The standby keeps a passive registrar and call
+//! router (no expiry timers, no media) until it is promoted through the
+//! API or after losing the active node's heartbeat.
+
+mod node;
+mod protocol;
+mod tls;
+
+pub use node::{PromoteError, ReplicationNode, ReplicationStatus};
+pub use protocol::{ReplicationFrame, ReplicationPayload, ReplicationRole};
+pub use tls::ReplicationTls;
+pub(crate) use tls::crypto_provider;
+
+use serde::{Deserialize, Serialize};
+
+/// Replication settings
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ReplicationConfig {
+    pub enabled: bool,
+    /// Role at startup; an active node that finds an active peer starts as standby
+    pub role: ReplicationRole,
+    /// Name of this node in frames and status
+    pub node_id: String,
+    /// Replication address of the peer (`host:port`)
+    pub peer_address: Option<String>,
+    /// Address the replication channel listens on
+    pub listen_address: String,
+    /// Certificate, key and CA of the replication channel
+    pub tls: Option<ReplicationTlsConfig>,
+    /// Milliseconds between heartbeats of the active node
+    pub heartbeat_interval_ms: u64,
+    /// Milliseconds without heartbeat after which the active node is lost
+    pub failover_timeout_ms: u64,
+    /// Seconds between full snapshots
+    pub snapshot_interval_secs: u64,
+    /// Promote automatically when the active node's heartbeat is lost
+    pub auto_failover: bool,
+}
+
+impl Default for ReplicationConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            role: ReplicationRole::Active,
+            node_id: "yakyak".to_string(),
+            peer_address: None,
+            listen_address: "0.0.0.0:5070".to_string(),
+            tls: None,
+            heartbeat_interval_ms: 1000,
+            failover_timeout_ms: 5000,
+            snapshot_interval_secs: 60,
+            auto_failover: false,
+        }
+    }
+}
+
+/// Replication channel TLS files (PEM); both nodes present their
+/// certificate and must be signed by `ca_path`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ReplicationTlsConfig {
+    pub cert_path: String,
+    pub key_path: String,
+    pub ca_path: String,
+    /// Name the peer's certificate is verified against
+    pub server_name: String,
+}
@@ -0,0 +1,836 @@
+//! Replication node
+//!
+//! Every node listens for its peer. While active, a node connects to the
+//! peer and sends a snapshot followed by each registration and call change,
+//! with heartbeats in between and periodic snapshots. While on standby, it
+//! applies what it receives to its passive registrar and call router.
+
+use super::protocol::{ReplicationFrame, ReplicationPayload, ReplicationRole};
+use super::{ReplicationConfig, ReplicationTls};
+use crate::application::events::{EventBus, EventEnvelope};
+use crate::domain::call::CallEvent;
+use crate::infrastructure::protocols::sip::call_router::CallRouter;
+use crate::infrastructure::protocols::sip::registrar::Registrar;
+use chrono::{DateTime, Utc};
+use metrics::{counter, gauge};
+use serde::Serialize;
+use std::fmt;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::io::{AsyncBufReadExt, AsyncRead, AsyncWrite, AsyncWriteExt, BufReader};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::broadcast;
+use tokio::task::JoinHandle;
+use tracing::{debug, error, info, warn};
+
+/// Interval of the expiry sweep of an active node's registrar
+const EXPIRY_SWEEP_INTERVAL: Duration = Duration::from_secs(5);
+
+trait ReplicationStream: AsyncRead + AsyncWrite + Unpin + Send {}
+
+impl<T: AsyncRead + AsyncWrite + Unpin + Send> ReplicationStream for T {}
+
+type BoxedStream = Box<dyn ReplicationStream>;
+
+/// Promotion refused
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PromoteError {
+    /// The peer is still reachable and claims to be active
+    PeerActive(String),
+}
+
+impl fmt::Display for PromoteError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::PeerActive(reason) => write!(f, "Peer is still active: {}", reason),
+        }
+    }
+}
+
+impl std::error::Error for PromoteError {}
+
+/// Replication status (for the API)
+#[derive(Debug, Clone, Serialize)]
+pub struct ReplicationStatus {
+    pub node_id: String,
+    pub role: ReplicationRole,
+    pub peer_address: Option<String>,
+    pub peer_connected: bool,
+    /// Sequence of the last frame applied (standby)
+    pub last_sequence: u64,
+    /// When the active node last sent a frame (standby)
+    pub last_heartbeat_at: Option<DateTime<Utc>>,
+    /// Delay of the last frame applied (standby)
+    pub lag_ms: Option<i64>,
+    pub resyncs: u64,
+    pub registrations: usize,
+    pub calls: usize,
+}
+
+/// What a standby has received from the active node
+#[derive(Debug, Default)]
+struct ReceiveState {
+    last_sequence: u64,
+    /// A snapshot was applied since the last connection
+    synced: bool,
+    resync_requested: bool,
+    last_frame: Option<Instant>,
+    last_frame_at: Option<DateTime<Utc>>,
+    lag_ms: Option<i64>,
+    resyncs: u64,
+}
+
+/// Hot standby replication node
+pub struct ReplicationNode {
+    config: ReplicationConfig,
+    registrar: Arc<Registrar>,
+    call_router: Arc<CallRouter>,
+    call_events: Option<Arc<dyn EventBus>>,
+    tls: Option<ReplicationTls>,
+    role: Mutex<ReplicationRole>,
+    sequence: AtomicU64,
+    received: Mutex<ReceiveState>,
+    peer_connected: AtomicBool,
+    tasks: Mutex<Vec<JoinHandle<()>>>,
+}
+
+impl ReplicationNode {
+    pub fn new(
+        config: ReplicationConfig,
+        registrar: Arc<Registrar>,
+        call_router: Arc<CallRouter>,
+    ) -> Self {
+        let role = config.role;
+        Self {
+            config,
+            registrar,
+            call_router,
+            call_events: None,
+            tls: None,
+            role: Mutex::new(role),
+            sequence: AtomicU64::new(0),
+            received: Mutex::new(ReceiveState::default()),
+            peer_connected: AtomicBool::new(false),
+            tasks: Mutex::new(Vec::new()),
+        }
+    }
+
+    /// Encrypt and authenticate the channel (plain TCP otherwise)
+    pub fn with_tls(mut self, tls: ReplicationTls) -> Self {
+        self.tls = Some(tls);
+        self
+    }
+
+    /// Replicate calls as their events are published
+    ///
+    /// Without it, calls only reach the standby with snapshots.
+    pub fn with_call_events(mut self, call_events: Arc<dyn EventBus>) -> Self {
+        self.call_events = Some(call_events);
+        self
+    }
+
+    pub fn role(&self) -> ReplicationRole {
+        *self.role.lock().unwrap()
+    }
+
+    pub fn is_active(&self) -> bool {
+        self.role() == ReplicationRole::Active
+    }
+
+    /// Listen for the peer and start replicating; returns the listen address
+    ///
+    /// A node configured active that finds its peer active starts as
+    /// standby instead.
+    pub async fn start(self: &Arc<Self>) -> Result<std::net::SocketAddr, String> {
+        let listener = TcpListener::bind(&self.config.listen_address)
+            .await
+            .map_err(|e| {
+                format!(
+                    "Failed to bind replication listener {}: {}",
+                    self.config.listen_address, e
+                )
+            })?;
+        let local_addr = listener
+            .local_addr()
+            .map_err(|e| format!("Failed to get replication listen address: {}", e))?;
+
+        if self.is_active() && self.probe_peer().await == Some(ReplicationRole::Active) {
+            warn!("Replication peer is already active - starting as standby");
+            *self.role.lock().unwrap() = ReplicationRole::Standby;
+        }
+
+        if self.is_active() {
+            self.registrar.set_passive(false);
+            self.spawn_expiry_sweeper();
+        } else {
+            self.registrar.set_passive(true);
+        }
+        gauge!("replication_active").set(if self.is_active() { 1.0 } else { 0.0 });
+        info!(
+            "Replication node {} listening on {} as {:?}",
+            self.config.node_id,
+            local_addr,
+            self.role()
+        );
+
+        let node = self.clone();
+        self.track(tokio::spawn(
+            async move { node.accept_loop(listener).await },
+        ));
+        if self.config.peer_address.is_some() {
+            let node = self.clone();
+            self.track(tokio::spawn(async move { node.sender_loop().await }));
+        }
+        if self.config.auto_failover {
+            let node = self.clone();
+            self.track(tokio::spawn(async move { node.failover_monitor().await }));
+        }
+
+        Ok(local_addr)
+    }
+
+    /// Stop listening and replicating
+    pub fn shutdown(&self) {
+        for task in self.tasks.lock().unwrap().drain(..) {
+            task.abort();
+        }
+        self.peer_connected.store(false, Ordering::SeqCst);
+    }
+
+    /// Become the active node
+    ///
+    /// Refused while the active node's frames are still arriving or while
+    /// the peer answers a probe as active, so both nodes never serve at once.
+    pub async fn promote(&self) -> Result<(), PromoteError> {
+        if self.is_active() {
+            return Ok(());
+        }
+
+        if let Some(age) = self.last_frame_age() {
+            if age < self.failover_timeout() {
+                return Err(PromoteError::PeerActive(format!(
+                    "last heartbeat {}ms ago",
+                    age.as_millis()
+                )));
+            }
+        }
+        if self.probe_peer().await == Some(ReplicationRole::Active) {
+            return Err(PromoteError::PeerActive(
+                "peer answered as active".to_string(),
+            ));
+        }
+
+        *self.role.lock().unwrap() = ReplicationRole::Active;
+        self.registrar.set_passive(false);
+        let purged = self.registrar.purge_expired().await;
+        self.spawn_expiry_sweeper();
+
+        counter!("replication_promotions_total").increment(1);
+        gauge!("replication_active").set(1.0);
+        info!(
+            "Replication node {} promoted to active ({} registrations, {} calls, {} expired bindings purged)",
+            self.config.node_id,
+            self.registrar.get_registration_count().await,
+            self.call_router.active_call_count().await,
+            purged
+        );
+        Ok(())
+    }
+
+    pub async fn status(&self) -> ReplicationStatus {
+        let (last_sequence, last_heartbeat_at, lag_ms, resyncs) = {
+            let received = self.received.lock().unwrap();
+            (
+                received.last_sequence,
+                received.last_frame_at,
+                received.lag_ms,
+                received.resyncs,
+            )
+        };
+        ReplicationStatus {
+            node_id: self.config.node_id.clone(),
+            role: self.role(),
+            peer_address: self.config.peer_address.clone(),
+            peer_connected: self.peer_connected.load(Ordering::SeqCst),
+            last_sequence,
+            last_heartbeat_at,
+            lag_ms,
+            resyncs,
+            registrations: self.registrar.get_registration_count().await,
+            calls: self.call_router.active_call_count().await,
+        }
+    }
+
+    fn track(&self, task: JoinHandle<()>) {
+        self.tasks.lock().unwrap().push(task);
+    }
+
+    fn heartbeat_interval(&self) -> Duration {
+        Duration::from_millis(self.config.heartbeat_interval_ms.max(1))
+    }
+
+    fn failover_timeout(&self) -> Duration {
+        Duration::from_millis(self.config.failover_timeout_ms)
+    }
+
+    fn last_frame_age(&self) -> Option<Duration> {
+        self.received
+            .lock()
+            .unwrap()
+            .last_frame
+            .map(|at| at.elapsed())
+    }
+
+    fn spawn_expiry_sweeper(&self) {
+        let registrar = self.registrar.clone();
+        self.track(tokio::spawn(async move {
+            let mut ticker = tokio::time::interval(EXPIRY_SWEEP_INTERVAL);
+            loop {
+                ticker.tick().await;
+                let purged = registrar.purge_expired().await;
+                if purged > 0 {
+                    debug!("Purged {} expired bindings", purged);
+                }
+            }
+        }));
+    }
+
+    fn frame(&self, payload: ReplicationPayload) -> ReplicationFrame {
+        ReplicationFrame {
+            sequence: self.sequence.fetch_add(1, Ordering::SeqCst) + 1,
+            sent_at: Utc::now(),
+            node_id: self.config.node_id.clone(),
+            role: self.role(),
+            payload,
+        }
+    }
+
+    async fn send<W: AsyncWrite + Unpin>(
+        &self,
+        writer: &mut W,
+        payload: ReplicationPayload,
+    ) -> Result<(), String> {
+        let line = self.frame(payload).encode()?;
+        writer
+            .write_all(line.as_bytes())
+            .await
+            .map_err(|e| format!("Failed to send replication frame: {}", e))?;
+        writer
+            .flush()
+            .await
+            .map_err(|e| format!("Failed to send replication frame: {}", e))
+    }
+
+    async fn connect(&self, peer: &str) -> Result<BoxedStream, String> {
+        let tcp = tokio::time::timeout(self.failover_timeout(), TcpStream::connect(peer))
+            .await
+            .map_err(|_| format!("Timed out connecting to {}", peer))?
+            .map_err(|e| format!("Failed to connect to {}: {}", peer, e))?;
+        let _ = tcp.set_nodelay(true);
+
+        match &self.tls {
+            Some(tls) => {
+                let stream = tls
+                    .connector
+                    .connect(tls.server_name.clone(), tcp)
+                    .await
+                    .map_err(|e| format!("TLS handshake with {} failed: {}", peer, e))?;
+                Ok(Box::new(stream))
+            }
+            None => Ok(Box::new(tcp)),
+        }
+    }
+
+    /// Role the peer claims, if it answers a heartbeat in time
+    async fn probe_peer(&self) -> Option<ReplicationRole> {
+        let peer = self.config.peer_address.as_ref()?;
+        let probe = async {
+            let stream = self.connect(peer).await?;
+            let (reader, mut writer) = tokio::io::split(stream);
+            self.send(&mut writer, ReplicationPayload::Heartbeat)
+                .await?;
+
+            let mut lines = BufReader::new(reader).lines();
+            while let Some(line) = lines.next_line().await.map_err(|e| e.to_string())? {
+                let frame = ReplicationFrame::decode(&line)?;
+                if matches!(frame.payload, ReplicationPayload::Heartbeat) {
+                    return Ok(frame.role);
+                }
+            }
+            Err("connection closed".to_string())
+        };
+
+        match tokio::time::timeout(self.failover_timeout(), probe).await {
+            Ok(Ok(role)) => Some(role),
+            Ok(Err(e)) => {
+                debug!("Replication peer {} did not answer probe: {}", peer, e);
+                None
+            }
+            Err(_) => None,
+        }
+    }
+
+    async fn accept_loop(self: Arc<Self>, listener: TcpListener) {
+        loop {
+            let (tcp, peer) = match listener.accept().await {
+                Ok(accepted) => accepted,
+                Err(e) => {
+                    error!("Replication listener failed: {}", e);
+                    continue;
+                }
+            };
+            let node = self.clone();
+            tokio::spawn(async move {
+                let stream: BoxedStream = match &node.tls {
+                    Some(tls) => match tls.acceptor.accept(tcp).await {
+                        Ok(stream) => Box::new(stream),
+                        Err(e) => {
+                            warn!("Replication TLS handshake with {} failed: {}", peer, e);
+                            return;
+                        }
+                    },
+                    None => Box::new(tcp),
+                };
+                if let Err(e) = node.serve_peer(stream).await {
+                    warn!("Replication connection from {} failed: {}", peer, e);
+                }
+            });
+        }
+    }
+
+    /// Apply frames from an active peer; answer probes with our role
+    async fn serve_peer(&self, stream: BoxedStream) -> Result<(), String> {
+        let (reader, mut writer) = tokio::io::split(stream);
+        let mut lines = BufReader::new(reader).lines();
+        let mut replicating = false;
+
+        let result = async {
+            while let Some(line) = lines.next_line().await.map_err(|e| e.to_string())? {
+                let frame = ReplicationFrame::decode(&line)?;
+
+                if frame.role == ReplicationRole::Active && !self.is_active() {
+                    if !replicating {
+                        replicating = true;
+                        self.peer_connected.store(true, Ordering::SeqCst);
+                        info!("Replicating from active node {}", frame.node_id);
+                    }
+                    if let Some(reply) = self.apply(frame).await {
+                        self.send(&mut writer, reply).await?;
+                    }
+                    continue;
+                }
+
+                if frame.role == ReplicationRole::Active {
+                    warn!(
+                        "Replication peer {} claims active while this node is active",
+                        frame.node_id
+                    );
+                    counter!("replication_split_brain_total").increment(1);
+                }
+                if matches!(frame.payload, ReplicationPayload::Heartbeat) {
+                    self.send(&mut writer, ReplicationPayload::Heartbeat)
+                        .await?;
+                }
+            }
+            Ok(())
+        }
+        .await;
+
+        if replicating {
+            self.peer_connected.store(false, Ordering::SeqCst);
+            self.received.lock().unwrap().synced = false;
+            info!("Replication from active node stopped");
+        }
+        result
+    }
+
+    /// Apply a frame from the active node; returns the reply to send, if any
+    async fn apply(&self, frame: ReplicationFrame) -> Option<ReplicationPayload> {
+        let is_snapshot = matches!(frame.payload, ReplicationPayload::Snapshot { .. });
+        let lag_ms = (Utc::now() - frame.sent_at).num_milliseconds().max(0);
+
+        let resync = {
+            let mut received = self.received.lock().unwrap();
+            let gap = !received.synced || frame.sequence != received.last_sequence + 1;
+            received.last_sequence = frame.sequence;
+            received.last_frame = Some(Instant::now());
+            received.last_frame_at = Some(frame.sent_at);
+            received.lag_ms = Some(lag_ms);
+
+            if is_snapshot {
+                received.synced = true;
+                received.resync_requested = false;
+                false
+            } else if gap && !received.resync_requested {
+                received.resync_requested = true;
+                received.resyncs += 1;
+                true
+            } else {
+                false
+            }
+        };
+
+        gauge!("replication_lag_seconds").set(lag_ms as f64 / 1000.0);
+        gauge!("replication_applied_sequence").set(frame.sequence as f64);
+
+        match frame.payload {
+            ReplicationPayload::Snapshot {
+                registrations,
+                calls,
+            } => {
+                debug!(
+                    "Applying replication snapshot {} ({} registrations, {} calls)",
+                    frame.sequence,
+                    registrations.len(),
+                    calls.len()
+                );
+                self.registrar.restore(registrations).await;
+                if let Err(e) = self.call_router.restore_calls(calls).await {
+                    warn!("Failed to restore replicated calls: {}", e);
+                }
+            }
+            ReplicationPayload::Registration { aor, bindings } => {
+                self.registrar.replace_registration(&aor, bindings).await;
+            }
+            ReplicationPayload::Call { call } => {
+                let call_id = call.call_id.clone();
+                if let Err(e) = self.call_router.restore_call(call).await {
+                    warn!("Failed to restore replicated call {}: {}", call_id, e);
+                }
+            }
+            ReplicationPayload::CallEnded { call_id } => {
+                self.call_router.remove_restored_call(&call_id).await;
+            }
+            ReplicationPayload::Heartbeat | ReplicationPayload::Resync => {}
+        }
+
+        if resync {
+            counter!("replication_resyncs_total").increment(1);
+            warn!(
+                "Replication gap before frame {} - requesting snapshot",
+                frame.sequence
+            );
+            return Some(ReplicationPayload::Resync);
+        }
+        None
+    }
+
+    /// Replicate to the peer while active, reconnecting after failures
+    async fn sender_loop(self: Arc<Self>) {
+        let peer = match &self.config.peer_address {
+            Some(peer) => peer.clone(),
+            None => return,
+        };
+        loop {
+            if self.is_active() {
+                match self.connect(&peer).await {
+                    Ok(stream) => {
+                        info!("Replicating to standby {}", peer);
+                        if let Err(e) = self.replicate(stream).await {
+                            warn!("Replication to {} interrupted: {}", peer, e);
+                        }
+                        self.peer_connected.store(false, Ordering::SeqCst);
+                    }
+                    Err(e) => debug!("Replication peer {} unreachable: {}", peer, e),
+                }
+            }
+            tokio::time::sleep(self.heartbeat_interval()).await;
+        }
+    }
+
+    async fn replicate(&self, stream: BoxedStream) -> Result<(), String> {
+        let (reader, mut writer) = tokio::io::split(stream);
+        let mut lines = BufReader::new(reader).lines();
+
+        // Subscribe before the snapshot so no change falls in between
+        let mut registrations = self.registrar.subscribe_events();
+        let mut calls = self.call_events.as_ref().map(|bus| bus.subscribe());
+
+        self.peer_connected.store(true, Ordering::SeqCst);
+        self.send(&mut writer, self.snapshot().await).await?;
+
+        let mut heartbeat = tokio::time::interval(self.heartbeat_interval());
+        let mut snapshot = tokio::time::interval(Duration::from_secs(
+            self.config.snapshot_interval_secs.max(1),
+        ));
+        heartbeat.tick().await;
+        snapshot.tick().await;
+
+        while self.is_active() {
+            let payload = tokio::select! {
+                _ = heartbeat.tick() => ReplicationPayload::Heartbeat,
+                _ = snapshot.tick() => self.snapshot().await,
+                line = lines.next_line() => {
+                    let line = line
+                        .map_err(|e| e.to_string())?
+                        .ok_or_else(|| "Standby closed the connection".to_string())?;
+                    let frame = ReplicationFrame::decode(&line)?;
+                    match frame.payload {
+                        ReplicationPayload::Resync => {
+                            info!("Standby requested a snapshot");
+                            self.snapshot().await
+                        }
+                        ReplicationPayload::Heartbeat if frame.role == ReplicationRole::Active => {
+                            warn!("Replication peer {} claims active", frame.node_id);
+                            counter!("replication_split_brain_total").increment(1);
+                            continue;
+                        }
+                        _ => continue,
+                    }
+                }
+                event = registrations.recv() => match event {
+                    Ok(event) => self.registration(&event.aor).await,
+                    Err(broadcast::error::RecvError::Lagged(_)) => self.snapshot().await,
+                    Err(broadcast::error::RecvError::Closed) => {
+                        return Err("Registration events closed".to_string());
+                    }
+                },
+                event = next_call_event(&mut calls) => match event {
+                    Ok(envelope) => match self.call(&envelope).await {
+                        Some(payload) => payload,
+                        None => continue,
+                    },
+                    Err(broadcast::error::RecvError::Lagged(_)) => self.snapshot().await,
+                    Err(broadcast::error::RecvError::Closed) => {
+                        calls = None;
+                        continue;
+                    }
+                },
+            };
+            self.send(&mut writer, payload).await?;
+        }
+        Ok(())
+    }
+
+    async fn snapshot(&self) -> ReplicationPayload {
+        ReplicationPayload::Snapshot {
+            registrations: self.registrar.snapshot().await,
+            calls: self.call_router.checkpoints().await,
+        }
+    }
+
+    async fn registration(&self, aor: &str) -> ReplicationPayload {
+        let bindings = self
+            .registrar
+            .registration(aor)
+            .await
+            .map(|registration| registration.bindings)
+            .unwrap_or_default();
+        ReplicationPayload::Registration {
+            aor: aor.to_string(),
+            bindings,
+        }
+    }
+
+    async fn call(&self, envelope: &EventEnvelope) -> Option<ReplicationPayload> {
+        if let CallEvent::Ended(_) = envelope.event {
+            return Some(ReplicationPayload::CallEnded {
+                call_id: envelope.call_id.clone(),
+            });
+        }
+        // Not in the router yet while the call is being set up
+        let call = self.call_router.checkpoint(&envelope.call_id).await?;
+        Some(ReplicationPayload::Call { call })
+    }
+
+    /// Promote once the active node's frames stop arriving
+    async fn failover_monitor(self: Arc<Self>) {
+        let mut ticker = tokio::time::interval(self.heartbeat_interval());
+        loop {
+            ticker.tick().await;
+            if self.is_active() {
+                continue;
+            }
+            match self.last_frame_age() {
+                Some(age) if age >= self.failover_timeout() => {
+                    warn!(
+                        "No heartbeat from active node for {}ms - promoting",
+                        age.as_millis()
+                    );
+                    if let Err(e) = self.promote().await {
+                        warn!("Automatic promotion refused: {}", e);
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+}
+
+async fn next_call_event(
+    calls: &mut Option<broadcast::Receiver<EventEnvelope>>,
+) -> Result<EventEnvelope, broadcast::error::RecvError> {
+    match calls {
+        Some(receiver) => receiver.recv().await,
+        None => std::future::pending().await,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::application::call::CallApplicationService;
+    use crate::infrastructure::messaging::InProcessEventBus;
+    use crate::infrastructure::protocols::sip::call_state::CallState;
+
+    fn config(role: ReplicationRole, node_id: &str, peer: Option<String>) -> ReplicationConfig {
+        ReplicationConfig {
+            enabled: true,
+            role,
+            node_id: node_id.to_string(),
+            peer_address: peer,
+            listen_address: "127.0.0.1:0".to_string(),
+            tls: None,
+            heartbeat_interval_ms: 50,
+            failover_timeout_ms: 300,
+            snapshot_interval_secs: 60,
+            auto_failover: false,
+        }
+    }
+
+    async fn eventually<F, Fut>(mut check: F)
+    where
+        F: FnMut() -> Fut,
+        Fut: std::future::Future<Output = bool>,
+    {
+        for _ in 0..100 {
+            if check().await {
+                return;
+            }
+            tokio::time::sleep(Duration::from_millis(50)).await;
+        }
+        panic!("condition not reached");
+    }
+
+    #[tokio::test]
+    async fn test_binding_replicates_and_survives_promotion() {
+        let standby_registrar = Arc::new(Registrar::new());
+        let standby_router = Arc::new(CallRouter::new(standby_registrar.clone()));
+        let standby = Arc::new(ReplicationNode::new(
+            config(ReplicationRole::Standby, "b", None),
+            standby_registrar.clone(),
+            standby_router.clone(),
+        ));
+        let standby_addr = standby.start().await.unwrap();
+        assert!(standby_registrar.is_passive());
+
+        let bus: Arc<dyn EventBus> = Arc::new(InProcessEventBus::default());
+        let active_registrar = Arc::new(Registrar::new());
+        let active_router = Arc::new(
+            CallRouter::new(active_registrar.clone())
+                .with_call_events(Arc::new(CallApplicationService::new(bus.clone()))),
+        );
+        let active = Arc::new(
+            ReplicationNode::new(
+                config(ReplicationRole::Active, "a", Some(standby_addr.to_string())),
+                active_registrar.clone(),
+                active_router.clone(),
+            )
+            .with_call_events(bus),
+        );
+        active.start().await.unwrap();
+        assert!(active.is_active());
+
+        active_registrar
+            .add_binding(
+                "sip:alice@example.com".to_string(),
+                "sip:alice@192.0.2.10:5060".to_string(),
+                3600,
+            )
+            .await
+            .unwrap();
+        active_router
+            .create_call(
+                "call-1".to_string(),
+                "sip:alice@example.com".to_string(),
+                "sip:bob@example.com".to_string(),
+            )
+            .await
+            .unwrap();
+        active_router.answer_call("call-1").await.unwrap();
+
+        eventually(|| async {
+            standby_registrar
+                .get_bindings("sip:alice@example.com")
+                .await
+                .is_some()
+                && standby_router.get_call_state("call-1").await == Some(CallState::Established)
+        })
+        .await;
+        assert!(standby.status().await.peer_connected);
+
+        // Split-brain guard: the active node is still sending
+        assert!(matches!(
+            standby.promote().await,
+            Err(PromoteError::PeerActive(_))
+        ));
+        assert!(!standby.is_active());
+
+        // Active node fails; once its heartbeat is lost, promotion succeeds
+        active.shutdown();
+        tokio::time::sleep(Duration::from_millis(400)).await;
+        standby.promote().await.unwrap();
+        assert!(standby.is_active());
+        assert!(!standby_registrar.is_passive());
+
+        let bindings = standby_registrar
+            .get_bindings("sip:alice@example.com")
+            .await
+            .unwrap();
+        assert_eq!(bindings[0].contact, "sip:alice@192.0.2.10:5060");
+        // In-dialog BYE for the replicated call is handled
+        standby_router.terminate_call("call-1").await.unwrap();
+
+        standby.shutdown();
+    }
+
+    #[tokio::test]
+    async fn test_sequence_gap_requests_resync() {
+        let registrar = Arc::new(Registrar::new());
+        let router = Arc::new(CallRouter::new(registrar.clone()));
+        let node = ReplicationNode::new(
+            config(ReplicationRole::Standby, "b", None),
+            registrar.clone(),
+            router,
+        );
+        let frame = |sequence, payload| ReplicationFrame {
+            sequence,
+            sent_at: Utc::now(),
+            node_id: "a".to_string(),
+            role: ReplicationRole::Active,
+            payload,
+        };
+
+        let snapshot = ReplicationPayload::Snapshot {
+            registrations: Vec::new(),
+            calls: Vec::new(),
+        };
+        assert!(node.apply(frame(1, snapshot)).await.is_none());
+        assert!(node
+            .apply(frame(2, ReplicationPayload::Heartbeat))
+            .await
+            .is_none());
+
+        // Frame 3 was lost
+        let reply = node
+            .apply(frame(
+                4,
+                ReplicationPayload::Registration {
+                    aor: "sip:alice@example.com".to_string(),
+                    bindings: Vec::new(),
+                },
+            ))
+            .await;
+        assert!(matches!(reply, Some(ReplicationPayload::Resync)));
+        // Asked once until the snapshot arrives
+        assert!(node
+            .apply(frame(6, ReplicationPayload::Heartbeat))
+            .await
+            .is_none());
+
+        let status = node.status().await;
+        assert_eq!(status.last_sequence, 6);
+        assert_eq!(status.resyncs, 1);
+    }
+}
@@ -0,0 +1,71 @@
+//! Replication wire format
+//!
+//! Frames are JSON objects, one per line. Every frame a node sends carries
+//! the next number of that node's sequence; a standby that sees a gap asks
+//! for a full snapshot with `Resync`.
+
+use crate::infrastructure::protocols::sip::call_router::CallCheckpoint;
+use crate::infrastructure::protocols::sip::registrar::{Binding, Registration};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+/// Role of a node
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ReplicationRole {
+    Active,
+    Standby,
+}
+
+/// Replicated state change
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ReplicationPayload {
+    /// Full state; replaces everything replicated before
+    Snapshot {
+        registrations: Vec<Registration>,
+        calls: Vec<CallCheckpoint>,
+    },
+    /// All bindings of an AoR (none: unregistered)
+    Registration {
+        aor: String,
+        bindings: Vec<Binding>,
+    },
+    /// Call created or updated
+    Call {
+        call: CallCheckpoint,
+    },
+    CallEnded {
+        call_id: String,
+    },
+    /// Liveness (and, answered with the peer's own, a role probe)
+    Heartbeat,
+    /// Standby lost frames and needs a snapshot
+    Resync,
+}
+
+/// Replication frame
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ReplicationFrame {
+    pub sequence: u64,
+    pub sent_at: DateTime<Utc>,
+    pub node_id: String,
+    /// Role of the sender when the frame was sent
+    pub role: ReplicationRole,
+    pub payload: ReplicationPayload,
+}
+
+impl ReplicationFrame {
+    /// Encode as one line
+    pub fn encode(&self) -> Result<String, String> {
+        let mut line = serde_json::to_string(self)
+            .map_err(|e| format!("Failed to encode replication frame: {}", e))?;
+        line.push('\n');
+        Ok(line)
+    }
+
+    pub fn decode(line: &str) -> Result<Self, String> {
+        serde_json::from_str(line.trim_end())
+            .map_err(|e| format!("Invalid replication frame: {}", e))
+    }
+}
@@ -0,0 +1,91 @@
+//! Replication channel TLS
+//!
+//! Both nodes present a certificate signed by the replication CA, so the
+//! listener only accepts its peer.
+
+use super::ReplicationTlsConfig;
+use rustls::crypto::CryptoProvider;
+use rustls::pki_types::{CertificateDer, PrivateKeyDer, ServerName};
+use rustls::server::WebPkiClientVerifier;
+use rustls::{ClientConfig, RootCertStore, ServerConfig};
+use rustls_pemfile::{certs, private_key};
+use std::fs::File;
+use std::io::BufReader;
+use std::sync::Arc;
+use tokio_rustls::{TlsAcceptor, TlsConnector};
+
+/// TLS acceptor and connector of the replication channel
+#[derive(Clone)]
+pub struct ReplicationTls {
+    pub acceptor: TlsAcceptor,
+    pub connector: TlsConnector,
+    pub server_name: ServerName<'static>,
+}
+
+impl ReplicationTls {
+    pub fn from_config(config: &ReplicationTlsConfig) -> Result<Self, String> {
+        let cert_chain = load_certs(&config.cert_path)?;
+        let key = load_key(&config.key_path)?;
+
+        let mut roots = RootCertStore::empty();
+        for ca in load_certs(&config.ca_path)? {
+            roots
+                .add(ca)
+                .map_err(|e| format!("Invalid CA certificate {}: {}", config.ca_path, e))?;
+        }
+        let roots = Arc::new(roots);
+
+        let provider = crypto_provider();
+        let verifier = WebPkiClientVerifier::builder_with_provider(roots.clone(), provider.clone())
+            .build()
+            .map_err(|e| format!("Failed to build client verifier: {}", e))?;
+        let server = ServerConfig::builder_with_provider(provider.clone())
+            .with_safe_default_protocol_versions()
+            .map_err(|e| format!("Unsupported TLS protocol versions: {}", e))?
+            .with_client_cert_verifier(verifier)
+            .with_single_cert(cert_chain.clone(), key.clone_key())
+            .map_err(|e| format!("Invalid replication certificate: {}", e))?;
+
+        let client = ClientConfig::builder_with_provider(provider)
+            .with_safe_default_protocol_versions()
+            .map_err(|e| format!("Unsupported TLS protocol versions: {}", e))?
+            .with_root_certificates(roots)
+            .with_client_auth_cert(cert_chain, key)
+            .map_err(|e| format!("Invalid replication certificate: {}", e))?;
+
+        let server_name = ServerName::try_from(config.server_name.clone())
+            .map_err(|e| format!("Invalid server name {}: {}", config.server_name, e))?;
+
+        Ok(Self {
+            acceptor: TlsAcceptor::from(Arc::new(server)),
+            connector: TlsConnector::from(Arc::new(client)),
+            server_name,
+        })
+    }
+}
+
+/// Crypto provider of the replication channel
+///
+/// Named explicitly: with both rustls backends compiled in there is no
+/// process-wide default to fall back on.
+pub(crate) fn crypto_provider() -> Arc<CryptoProvider> {
+    Arc::new(rustls::crypto::aws_lc_rs::default_provider())
+}
+
+fn load_certs(path: &str) -> Result<Vec<CertificateDer<'static>>, String> {
+    let file = File::open(path).map_err(|e| format!("Failed to open {}: {}", path, e))?;
+    let chain = certs(&mut BufReader::new(file))
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|e| format!("Failed to parse certificates in {}: {}", path, e))?;
+    if chain.is_empty() {
+        return Err(format!("No certificates found in {}", path));
+    }
+    Ok(chain)
+}
+
+fn load_key(path: &str) -> Result<PrivateKeyDer<'static>, String> {
+    let file = File::open(path).map_err(|e| format!("Failed to open {}: {}", path, e))?;
+    private_key(&mut BufReader::new(file))
+        .map_err(|e| format!("Failed to parse private key in {}: {}", path, e))?
+        .ok_or_else(|| format!("No private key found in {}", path))
+}
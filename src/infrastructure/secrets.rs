@@ -0,0 +1,386 @@
+//! Encryption of stored secrets
+//!
+//! Credentials written to the database (trunk passwords) are sealed with
+//! AES-256-GCM under the current key of the [`SecretKeyring`] and stored as
+//! `enc:v1:<key id>:<base64 of nonce and ciphertext>`. The key id in the
+//! envelope names the key a value was sealed with, so after a new key is
+//! made current the retired keys stay listed as `previous` and their values
+//! stay readable until `yakyak rotate-secrets` reseals them.
+//!
+//! Values stored before encryption was enabled are marked `plain:` by the
+//! migration and are read as they are; so are values written while no key
+//! is configured. Rotation seals them too.
+
+use crate::domain::shared::SecretBox;
+use aes_gcm::aead::{Aead, KeyInit, Payload};
+use aes_gcm::{Aes256Gcm, Key, Nonce};
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use rand::RngCore;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fmt;
+use std::path::PathBuf;
+use thiserror::Error;
+
+/// Length of an AES-256 key
+pub const KEY_LEN: usize = 32;
+const NONCE_LEN: usize = 12;
+const ENVELOPE_PREFIX: &str = "enc:v1:";
+const PLAIN_PREFIX: &str = "plain:";
+
+#[derive(Debug, Error, PartialEq, Eq)]
+pub enum SecretError {
+    #[error("secret key id {0:?} must be non-empty and must not contain ':'")]
+    InvalidKeyId(String),
+    #[error("secret key {0} needs exactly one of key, key_env and key_file")]
+    KeySource(String),
+    #[error("secret key {id} cannot be read: {reason}")]
+    KeyUnreadable { id: String, reason: String },
+    #[error("secret key {0} is not a base64-encoded 32-byte key")]
+    KeyInvalid(String),
+    #[error("secret key id {0} is listed twice")]
+    DuplicateKeyId(String),
+    #[error("secret is sealed with unknown key {0}")]
+    UnknownKey(String),
+    #[error("malformed secret envelope")]
+    MalformedEnvelope,
+    #[error("secret does not decrypt with key {0}")]
+    DecryptFailed(String),
+    #[error("secret is encrypted but no secret key is configured")]
+    NoKeyring,
+}
+
+/// Keys of stored secrets
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SecretsConfig {
+    /// Key new secrets are sealed with; secrets are stored in the clear
+    /// without one
+    pub current: Option<SecretKeyConfig>,
+    /// Retired keys, still read until rotation reseals their secrets
+    pub previous: Vec<SecretKeyConfig>,
+}
+
+impl SecretsConfig {
+    pub fn validate(&self) -> Result<(), String> {
+        SecretKeyring::from_config(self).map(|_| ()).map_err(|e| e.to_string())
+    }
+}
+
+/// One key and where it is loaded from
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SecretKeyConfig {
+    /// Written into every envelope sealed with the key
+    pub id: String,
+    /// Base64 of the 32-byte key
+    #[serde(default)]
+    pub key: Option<SecretBox>,
+    /// Environment variable holding the base64 key
+    #[serde(default)]
+    pub key_env: Option<String>,
+    /// File holding the base64 key, e.g. one written by a KMS agent
+    #[serde(default)]
+    pub key_file: Option<PathBuf>,
+}
+
+impl SecretKeyConfig {
+    /// Read and decode the key
+    pub fn load(&self) -> Result<[u8; KEY_LEN], SecretError> {
+        if self.id.is_empty() || self.id.contains(':') {
+            return Err(SecretError::InvalidKeyId(self.id.clone()));
+        }
+        let unreadable = |reason: String| SecretError::KeyUnreadable {
+            id: self.id.clone(),
+            reason,
+        };
+        let encoded = match (&self.key, &self.key_env, &self.key_file) {
+            (Some(key), None, None) => key.clone(),
+            (None, Some(var), None) => std::env::var(var)
+                .map(SecretBox::from)
+                .map_err(|e| unreadable(format!("{}: {}", var, e)))?,
+            (None, None, Some(path)) => std::fs::read_to_string(path)
+                .map(SecretBox::from)
+                .map_err(|e| unreadable(format!("{}: {}", path.display(), e)))?,
+            _ => return Err(SecretError::KeySource(self.id.clone())),
+        };
+        STANDARD
+            .decode(encoded.expose().trim())
+            .ok()
+            .and_then(|bytes| <[u8; KEY_LEN]>::try_from(bytes).ok())
+            .ok_or_else(|| SecretError::KeyInvalid(self.id.clone()))
+    }
+}
+
+/// Current and retired keys of stored secrets
+pub struct SecretKeyring {
+    current: String,
+    keys: HashMap<String, Aes256Gcm>,
+}
+
+impl SecretKeyring {
+    pub fn new(key_id: impl Into<String>, key: &[u8; KEY_LEN]) -> Self {
+        let current = key_id.into();
+        let mut keys = HashMap::new();
+        keys.insert(current.clone(), Aes256Gcm::new(&Key::<Aes256Gcm>::from(*key)));
+        Self { current, keys }
+    }
+
+    /// Also read secrets sealed with a retired key
+    pub fn with_previous_key(mut self, key_id: impl Into<String>, key: &[u8; KEY_LEN]) -> Self {
+        self.keys
+            .entry(key_id.into())
+            .or_insert_with(|| Aes256Gcm::new(&Key::<Aes256Gcm>::from(*key)));
+        self
+    }
+
+    /// Keyring of the configured keys; `None` when no current key is set
+    pub fn from_config(config: &SecretsConfig) -> Result<Option<Self>, SecretError> {
+        let Some(current) = &config.current else {
+            return Ok(None);
+        };
+        let mut keyring = Self::new(current.id.clone(), &current.load()?);
+        for previous in &config.previous {
+            let key = previous.load()?;
+            if keyring.keys.contains_key(&previous.id) {
+                return Err(SecretError::DuplicateKeyId(previous.id.clone()));
+            }
+            keyring = keyring.with_previous_key(previous.id.clone(), &key);
+        }
+        Ok(Some(keyring))
+    }
+
+    pub fn current_key_id(&self) -> &str {
+        &self.current
+    }
+
+    /// Encrypt under the current key
+    pub fn seal(&self, secret: &SecretBox) -> String {
+        let mut nonce = [0u8; NONCE_LEN];
+        rand::thread_rng().fill_bytes(&mut nonce);
+        let payload = Payload {
+            msg: secret.expose().as_bytes(),
+            aad: self.current.as_bytes(),
+        };
+        let ciphertext = self.keys[&self.current]
+            .encrypt(&Nonce::from(nonce), payload)
+            .expect("AES-GCM encryption of a stored secret");
+        let mut sealed = nonce.to_vec();
+        sealed.extend_from_slice(&ciphertext);
+        format!("{}{}:{}", ENVELOPE_PREFIX, self.current, STANDARD.encode(sealed))
+    }
+
+    /// Decrypt a stored secret, whichever known key sealed it
+    pub fn open(&self, stored: &str) -> Result<SecretBox, SecretError> {
+        let Some(envelope) = stored.strip_prefix(ENVELOPE_PREFIX) else {
+            return Ok(open_plain(stored));
+        };
+        let (key_id, encoded) = envelope.split_once(':').ok_or(SecretError::MalformedEnvelope)?;
+        let cipher = self
+            .keys
+            .get(key_id)
+            .ok_or_else(|| SecretError::UnknownKey(key_id.to_string()))?;
+        let sealed = STANDARD.decode(encoded).map_err(|_| SecretError::MalformedEnvelope)?;
+        let (nonce, ciphertext) = sealed
+            .split_first_chunk::<NONCE_LEN>()
+            .ok_or(SecretError::MalformedEnvelope)?;
+        let payload = Payload {
+            msg: ciphertext,
+            aad: key_id.as_bytes(),
+        };
+        cipher
+            .decrypt(&Nonce::from(*nonce), payload)
+            .ok()
+            .and_then(|plain| String::from_utf8(plain).ok())
+            .map(SecretBox::from)
+            .ok_or_else(|| SecretError::DecryptFailed(key_id.to_string()))
+    }
+
+    /// Whether a stored secret is not yet sealed with the current key
+    pub fn needs_reseal(&self, stored: &str) -> bool {
+        stored
+            .strip_prefix(ENVELOPE_PREFIX)
+            .and_then(|envelope| envelope.split_once(':'))
+            .is_none_or(|(key_id, _)| key_id != self.current)
+    }
+
+    /// The secret sealed with the current key; `None` when it already is
+    pub fn reseal(&self, stored: &str) -> Result<Option<String>, SecretError> {
+        if !self.needs_reseal(stored) {
+            return Ok(None);
+        }
+        self.open(stored).map(|secret| Some(self.seal(&secret)))
+    }
+}
+
+impl fmt::Debug for SecretKeyring {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut key_ids: Vec<&String> = self.keys.keys().collect();
+        key_ids.sort();
+        f.debug_struct("SecretKeyring")
+            .field("current", &self.current)
+            .field("key_ids", &key_ids)
+            .finish()
+    }
+}
+
+/// Stored form of a secret: sealed with the keyring, or marked `plain:`
+/// without one
+pub fn seal_secret(keyring: Option<&SecretKeyring>, secret: &SecretBox) -> String {
+    match keyring {
+        Some(keyring) => keyring.seal(secret),
+        None => format!("{}{}", PLAIN_PREFIX, secret.expose()),
+    }
+}
+
+/// Secret of a stored value written by [`seal_secret`] or before
+/// encryption was enabled
+pub fn open_secret(keyring: Option<&SecretKeyring>, stored: &str) -> Result<SecretBox, SecretError> {
+    match keyring {
+        Some(keyring) => keyring.open(stored),
+        None if stored.starts_with(ENVELOPE_PREFIX) => Err(SecretError::NoKeyring),
+        None => Ok(open_plain(stored)),
+    }
+}
+
+fn open_plain(stored: &str) -> SecretBox {
+    SecretBox::from(stored.strip_prefix(PLAIN_PREFIX).unwrap_or(stored))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::infrastructure::lnp::LnpConfig;
+    use crate::infrastructure::transcription::TranscriptionConfig;
+
+    const OLD_KEY: [u8; KEY_LEN] = [7; KEY_LEN];
+    const NEW_KEY: [u8; KEY_LEN] = [42; KEY_LEN];
+
+    #[test]
+    fn test_seal_and_open_round_trip() {
+        let keyring = SecretKeyring::new("k1", &OLD_KEY);
+        let secret = SecretBox::new("trunk-pa$$word");
+
+        let sealed = keyring.seal(&secret);
+        assert!(sealed.starts_with("enc:v1:k1:"));
+        assert!(!sealed.contains("trunk-pa$$word"));
+        assert_ne!(sealed, keyring.seal(&secret));
+        assert_eq!(keyring.open(&sealed).unwrap(), secret);
+
+        // Envelopes of another key id or with a changed ciphertext fail
+        let relabelled = sealed.replacen("k1", "k2", 1);
+        assert_eq!(
+            SecretKeyring::new("k2", &OLD_KEY).open(&relabelled),
+            Err(SecretError::DecryptFailed("k2".to_string()))
+        );
+        assert_eq!(
+            SecretKeyring::new("k1", &NEW_KEY).open(&sealed),
+            Err(SecretError::DecryptFailed("k1".to_string()))
+        );
+        assert_eq!(open_secret(None, &sealed), Err(SecretError::NoKeyring));
+    }
+
+    #[test]
+    fn test_rotation_reads_mixed_old_and_new_ciphertexts() {
+        let old = SecretKeyring::new("2025", &OLD_KEY);
+        let before = old.seal(&SecretBox::new("sealed before"));
+        let rotated = SecretKeyring::new("2026", &NEW_KEY).with_previous_key("2025", &OLD_KEY);
+        let after = rotated.seal(&SecretBox::new("sealed after"));
+        let legacy = seal_secret(None, &SecretBox::new("never sealed"));
+        assert_eq!(legacy, "plain:never sealed");
+
+        for (stored, expected) in [
+            (&before, "sealed before"),
+            (&after, "sealed after"),
+            (&legacy, "never sealed"),
+            (&"migrated by hand".to_string(), "migrated by hand"),
+        ] {
+            assert_eq!(rotated.open(stored).unwrap().expose(), expected);
+        }
+        assert!(rotated.needs_reseal(&before));
+        assert!(rotated.needs_reseal(&legacy));
+        assert!(!rotated.needs_reseal(&after));
+        assert_eq!(rotated.reseal(&after), Ok(None));
+
+        // Once resealed, the old key can be dropped
+        let new_only = SecretKeyring::new("2026", &NEW_KEY);
+        for (stored, expected) in [(&before, "sealed before"), (&legacy, "never sealed")] {
+            let resealed = rotated.reseal(stored).unwrap().unwrap();
+            assert!(resealed.starts_with("enc:v1:2026:"));
+            assert_eq!(new_only.open(&resealed).unwrap().expose(), expected);
+        }
+        assert_eq!(new_only.open(&before), Err(SecretError::UnknownKey("2025".to_string())));
+    }
+
+    #[test]
+    fn test_keyring_from_config() {
+        let var = "YAKYAK_TEST_SECRET_KEY_FROM_ENV";
+        std::env::set_var(var, STANDARD.encode(OLD_KEY));
+        let config = SecretsConfig {
+            current: Some(SecretKeyConfig {
+                id: "new".to_string(),
+                key: Some(SecretBox::new(STANDARD.encode(NEW_KEY))),
+                key_env: None,
+                key_file: None,
+            }),
+            previous: vec![SecretKeyConfig {
+                id: "old".to_string(),
+                key: None,
+                key_env: Some(var.to_string()),
+                key_file: None,
+            }],
+        };
+        let keyring = SecretKeyring::from_config(&config).unwrap().unwrap();
+        assert_eq!(keyring.current_key_id(), "new");
+        let sealed = SecretKeyring::new("old", &OLD_KEY).seal(&SecretBox::new("x"));
+        assert_eq!(keyring.open(&sealed).unwrap().expose(), "x");
+        assert!(SecretKeyring::from_config(&SecretsConfig::default()).unwrap().is_none());
+
+        let mut short = config.clone();
+        short.current.as_mut().unwrap().key = Some(SecretBox::new(STANDARD.encode([1u8; 16])));
+        assert!(short.validate().unwrap_err().contains("32-byte"));
+        let mut both = config.clone();
+        both.current.as_mut().unwrap().key_env = Some(var.to_string());
+        assert!(both.validate().is_err());
+    }
+
+    #[test]
+    fn test_debug_output_hides_keys_and_secrets() {
+        let encoded = STANDARD.encode(NEW_KEY);
+        let config = SecretsConfig {
+            current: Some(SecretKeyConfig {
+                id: "k1".to_string(),
+                key: Some(SecretBox::new(encoded.clone())),
+                key_env: None,
+                key_file: None,
+            }),
+            previous: Vec::new(),
+        };
+        let debug = format!("{:?}", config);
+        assert!(debug.contains("SecretBox(********)"));
+        assert!(!debug.contains(&encoded));
+        assert_eq!(serde_json::to_value(&config).unwrap()["current"]["key"], "********");
+
+        let keyring = SecretKeyring::from_config(&config).unwrap().unwrap();
+        assert_eq!(
+            format!("{:?}", keyring),
+            r#"SecretKeyring { current: "k1", key_ids: ["k1"] }"#
+        );
+
+        let lnp = LnpConfig {
+            api_key: Some(SecretBox::new("lnp-api-key")),
+            ..Default::default()
+        };
+        let debug = format!("{:?}", lnp);
+        assert!(debug.contains("api_key: Some(SecretBox(********))"));
+        assert!(!debug.contains("lnp-api-key"));
+
+        let transcription = TranscriptionConfig {
+            api_key: Some(SecretBox::new("asr-api-key")),
+            ..Default::default()
+        };
+        let debug = format!("{:?}", transcription);
+        assert!(debug.contains("api_key: Some(SecretBox(********))"));
+        assert!(!debug.contains("asr-api-key"));
+    }
+}
@@ -0,0 +1,325 @@
+//! Crash-safe sequence state of dialogs and media sessions
+//!
+//! A restarted (or failed-over) node must not reuse sequence numbers the
+//! previous process already sent: a BYE whose CSeq is not above the last
+//! request in the dialog is refused by the peer (RFC 3261 section 12.2.2),
+//! and RTP reusing an SSRC with a restarted sequence looks like a replay to
+//! the receiver.
+//!
+//! [`SequenceVault`] keeps the highest local CSeq of every dialog and the
+//! SSRC of every media session in memory; a background task writes them to
+//! disk in batches, so the call path never waits on the disk. A vault
+//! recovered from that file starts every dialog `cseq_margin` above its
+//! last written CSeq, which covers the requests sent after the last flush,
+//! and gives every recovered media session a new SSRC with random sequence
+//! and timestamp offsets.
+
+use crate::infrastructure::media::rtp::RtpSession;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::sync::Notify;
+use tokio::task::JoinHandle;
+use tracing::{error, info};
+
+/// Sequence persistence settings
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SequenceVaultConfig {
+    pub enabled: bool,
+    /// File the high-water marks are written to
+    pub state_path: String,
+    /// Milliseconds changes are batched before they are written
+    pub flush_interval_ms: u64,
+    /// Added to every recovered CSeq; must exceed the requests a dialog
+    /// can send within one flush interval
+    pub cseq_margin: u32,
+}
+
+impl Default for SequenceVaultConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            state_path: "data/sequences.json".to_string(),
+            flush_interval_ms: 200,
+            cseq_margin: 100,
+        }
+    }
+}
+
+impl SequenceVaultConfig {
+    pub fn validate(&self) -> Result<(), String> {
+        if self.state_path.trim().is_empty() {
+            return Err("state_path must not be empty".to_string());
+        }
+        if self.flush_interval_ms == 0 {
+            return Err("flush_interval_ms must be at least 1".to_string());
+        }
+        if self.cseq_margin == 0 {
+            return Err("cseq_margin must be at least 1".to_string());
+        }
+        Ok(())
+    }
+}
+
+/// Persisted sequence state
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SequenceSnapshot {
+    /// Highest local CSeq by dialog (Call-ID)
+    #[serde(default)]
+    pub cseq: HashMap<String, u32>,
+    /// SSRC by media session
+    #[serde(default)]
+    pub ssrc: HashMap<String, u32>,
+}
+
+#[derive(Default)]
+struct VaultState {
+    current: SequenceSnapshot,
+    /// SSRCs in use before the recovery, never reused
+    recovered_ssrc: HashMap<String, u32>,
+    /// Changed since the last flush
+    dirty: bool,
+}
+
+/// High-water marks of local CSeqs and RTP SSRCs
+pub struct SequenceVault {
+    state: Mutex<VaultState>,
+    cseq_margin: u32,
+    /// Where flushes go; in memory only without
+    path: Option<PathBuf>,
+    changed: Notify,
+}
+
+impl SequenceVault {
+    /// Empty vault, kept in memory
+    pub fn new(cseq_margin: u32) -> Self {
+        Self {
+            state: Mutex::new(VaultState::default()),
+            cseq_margin,
+            path: None,
+            changed: Notify::new(),
+        }
+    }
+
+    /// Write flushes to `path`
+    pub fn with_path(mut self, path: impl Into<PathBuf>) -> Self {
+        self.path = Some(path.into());
+        self
+    }
+
+    /// Vault continuing the state of a previous process
+    ///
+    /// Every dialog continues `cseq_margin` above its recorded CSeq, and
+    /// every media session gets a new SSRC when it is set up again.
+    pub fn recovered(snapshot: SequenceSnapshot, cseq_margin: u32) -> Self {
+        let vault = Self::new(cseq_margin);
+        {
+            let mut state = vault.state.lock().unwrap();
+            state.current.cseq = snapshot
+                .cseq
+                .into_iter()
+                .map(|(call_id, cseq)| (call_id, cseq.saturating_add(cseq_margin)))
+                .collect();
+            state.recovered_ssrc = snapshot.ssrc;
+            state.dirty = !state.current.cseq.is_empty();
+        }
+        vault
+    }
+
+    /// Vault for `config`, recovered from its state file when there is one
+    pub fn open(config: &SequenceVaultConfig) -> std::io::Result<Self> {
+        let path = PathBuf::from(&config.state_path);
+        let vault = if path.exists() {
+            let snapshot = Self::load_from_file(&path)?;
+            info!(
+                "Recovered sequence state of {} dialogs and {} media sessions from {:?}",
+                snapshot.cseq.len(),
+                snapshot.ssrc.len(),
+                path
+            );
+            Self::recovered(snapshot, config.cseq_margin)
+        } else {
+            Self::new(config.cseq_margin)
+        };
+        Ok(vault.with_path(path))
+    }
+
+    /// Read a state file written by [`flush`](Self::flush)
+    pub fn load_from_file(path: &Path) -> std::io::Result<SequenceSnapshot> {
+        let bytes = std::fs::read(path)?;
+        Ok(serde_json::from_slice(&bytes)?)
+    }
+
+    fn mark_dirty(&self, state: &mut VaultState) {
+        state.dirty = true;
+        self.changed.notify_one();
+    }
+
+    /// Highest local CSeq recorded for `call_id`
+    pub fn cseq(&self, call_id: &str) -> Option<u32> {
+        self.state.lock().unwrap().current.cseq.get(call_id).copied()
+    }
+
+    /// CSeq of the next request we send in `call_id`
+    ///
+    /// Dialogs without a mark start at 1, the CSeq of the INVITE that
+    /// created them, so their first request gets 2.
+    pub fn next_cseq(&self, call_id: &str) -> u32 {
+        let mut state = self.state.lock().unwrap();
+        let mark = state.current.cseq.entry(call_id.to_string()).or_insert(1);
+        *mark = mark.saturating_add(1);
+        let cseq = *mark;
+        self.mark_dirty(&mut state);
+        cseq
+    }
+
+    /// Record a CSeq sent in `call_id` by other means
+    pub fn observe_cseq(&self, call_id: &str, cseq: u32) {
+        let mut state = self.state.lock().unwrap();
+        let mark = state.current.cseq.entry(call_id.to_string()).or_insert(0);
+        if cseq > *mark {
+            *mark = cseq;
+            self.mark_dirty(&mut state);
+        }
+    }
+
+    /// Continue `call_id` above `cseq` reported by the node it was taken
+    /// over from, leaving `cseq_margin` for requests it sent since
+    pub fn restore_cseq(&self, call_id: &str, cseq: u32) {
+        if cseq > 0 {
+            self.observe_cseq(call_id, cseq.saturating_add(self.cseq_margin));
+        }
+    }
+
+    /// RTP session of media session `key`
+    ///
+    /// A session that was in use before the recovery gets a new SSRC, see
+    /// [`RtpSession::recovered`].
+    pub fn rtp_session(&self, key: &str, payload_type: u8, clock_rate: u32) -> RtpSession {
+        let mut state = self.state.lock().unwrap();
+        let session = match state.recovered_ssrc.remove(key) {
+            Some(previous) => RtpSession::recovered(previous, payload_type, clock_rate),
+            None => RtpSession::new(payload_type, clock_rate),
+        };
+        state.current.ssrc.insert(key.to_string(), session.ssrc());
+        self.mark_dirty(&mut state);
+        session
+    }
+
+    /// Forget a dialog that ended, with its media session
+    pub fn forget(&self, call_id: &str) {
+        let mut state = self.state.lock().unwrap();
+        let removed = state.current.cseq.remove(call_id).is_some()
+            | state.current.ssrc.remove(call_id).is_some();
+        state.recovered_ssrc.remove(call_id);
+        if removed {
+            self.mark_dirty(&mut state);
+        }
+    }
+
+    /// Current state, as it would be written
+    pub fn snapshot(&self) -> SequenceSnapshot {
+        self.state.lock().unwrap().current.clone()
+    }
+
+    /// Write the state when it changed since the last flush
+    pub async fn flush(&self) -> std::io::Result<()> {
+        let Some(path) = &self.path else {
+            return Ok(());
+        };
+        let snapshot = {
+            let mut state = self.state.lock().unwrap();
+            if !state.dirty {
+                return Ok(());
+            }
+            state.dirty = false;
+            state.current.clone()
+        };
+        let result = Self::write(path, &snapshot).await;
+        if result.is_err() {
+            self.state.lock().unwrap().dirty = true;
+        }
+        result
+    }
+
+    async fn write(path: &Path, snapshot: &SequenceSnapshot) -> std::io::Result<()> {
+        let json = serde_json::to_vec(snapshot)?;
+        if let Some(parent) = path.parent() {
+            tokio::fs::create_dir_all(parent).await?;
+        }
+        // Write then rename so a crash never leaves a truncated file
+        let tmp = path.with_extension("tmp");
+        tokio::fs::write(&tmp, json).await?;
+        tokio::fs::rename(tmp, path).await
+    }
+
+    /// Flush changes, batched over `interval`, until the task is aborted
+    pub fn spawn_flusher(self: Arc<Self>, interval: Duration) -> JoinHandle<()> {
+        tokio::spawn(async move {
+            loop {
+                self.changed.notified().await;
+                tokio::time::sleep(interval).await;
+                if let Err(e) = self.flush().await {
+                    error!("Failed to persist sequence state to {:?}: {}", self.path, e);
+                }
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use uuid::Uuid;
+
+    fn state_path() -> PathBuf {
+        std::env::temp_dir()
+            .join(format!("yakyak-sequences-{}", Uuid::new_v4()))
+            .join("sequences.json")
+    }
+
+    #[tokio::test]
+    async fn test_recovered_dialogs_continue_above_unflushed_cseqs() {
+        let path = state_path();
+        let vault = SequenceVault::new(100).with_path(&path);
+        for _ in 0..5 {
+            vault.next_cseq("call-1");
+        }
+        vault.observe_cseq("call-2", 40);
+        vault.flush().await.unwrap();
+        // Sent after the last flush, lost in the crash
+        let lost: Vec<u32> = (0..20).map(|_| vault.next_cseq("call-1")).collect();
+        let highest = *lost.last().unwrap();
+        drop(vault);
+
+        let config = SequenceVaultConfig {
+            state_path: path.to_string_lossy().into_owned(),
+            ..Default::default()
+        };
+        let recovered = SequenceVault::open(&config).unwrap();
+        assert_eq!(recovered.cseq("call-1"), Some(6 + 100));
+        assert!(recovered.next_cseq("call-1") > highest);
+        assert_eq!(recovered.next_cseq("call-2"), 141);
+        assert_eq!(recovered.next_cseq("call-3"), 2);
+
+        let _ = std::fs::remove_dir_all(path.parent().unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_recovered_media_sessions_get_a_new_ssrc() {
+        let vault = SequenceVault::new(100);
+        let ssrc = vault.rtp_session("call-1", 0, 8000).ssrc();
+        assert_eq!(vault.snapshot().ssrc.get("call-1"), Some(&ssrc));
+
+        let recovered = SequenceVault::recovered(vault.snapshot(), 100);
+        let session = recovered.rtp_session("call-1", 0, 8000);
+        assert_ne!(session.ssrc(), ssrc);
+        assert_eq!(recovered.snapshot().ssrc.get("call-1"), Some(&session.ssrc()));
+
+        recovered.forget("call-1");
+        assert!(recovered.snapshot().ssrc.is_empty());
+    }
+}
@@ -0,0 +1,861 @@
+//! Call setup latency budgets
+//!
+//! Every call carries a [`SetupTimeline`] of monotonic clock reads: the
+//! INVITE received, the caller authenticated, the routing decision, the
+//! first 180/183 sent and the answer. Once a call's setup is over (it is
+//! answered or ends) the router hands its timeline to
+//! [`SetupLatencyMonitor::record`], which files the time from the INVITE to
+//! each milestone reached under the call's tenant and trunk.
+//!
+//! An evaluator checks each configured SLO every `evaluation_interval_secs`:
+//! a series (all calls, a tenant or a trunk) whose percentile over the
+//! window exceeds the threshold is `breached`, and `recovered` once back
+//! within it. Series with fewer than `min_samples` calls in the window keep
+//! their state. Events are published to subscribers, kept in a bounded
+//! history and, with a `webhook_url`, POSTed there as JSON (plain HTTP,
+//! like the LNP client).
+
+use crate::infrastructure::clock::{system_clock, Clock};
+use chrono::{DateTime, Utc};
+use metrics::histogram;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, VecDeque};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+use tokio::sync::broadcast;
+use tokio::task::JoinHandle;
+use tokio::time::Instant;
+use tracing::{info, warn};
+
+/// Samples kept per series; the oldest are dropped beyond it
+const MAX_SAMPLES: usize = 10_000;
+
+/// Upper bounds of the histogram buckets reported, in milliseconds
+const BUCKETS_MS: [u64; 10] = [50, 100, 200, 500, 1_000, 2_000, 5_000, 10_000, 30_000, 60_000];
+
+/// Point of call setup, timed from the INVITE
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SetupMilestone {
+    /// The caller's credentials were verified
+    Authenticated,
+    /// The call was routed to its destination
+    Routed,
+    /// First 180 Ringing or 183 Session Progress sent to the caller
+    FirstProvisional,
+    Answered,
+}
+
+impl SetupMilestone {
+    pub const ALL: [SetupMilestone; 4] = [
+        SetupMilestone::Authenticated,
+        SetupMilestone::Routed,
+        SetupMilestone::FirstProvisional,
+        SetupMilestone::Answered,
+    ];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            SetupMilestone::Authenticated => "authenticated",
+            SetupMilestone::Routed => "routed",
+            SetupMilestone::FirstProvisional => "first_provisional",
+            SetupMilestone::Answered => "answered",
+        }
+    }
+}
+
+impl std::fmt::Display for SetupMilestone {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// When a call reached each milestone of its setup
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SetupTimeline {
+    pub invite_received: std::time::Instant,
+    pub authenticated: Option<std::time::Instant>,
+    pub routed: Option<std::time::Instant>,
+    pub first_provisional: Option<std::time::Instant>,
+    pub answered: Option<std::time::Instant>,
+}
+
+impl SetupTimeline {
+    /// Timeline of a call whose INVITE was received at `invite_received`
+    pub fn starting_at(invite_received: std::time::Instant) -> Self {
+        Self {
+            invite_received,
+            authenticated: None,
+            routed: None,
+            first_provisional: None,
+            answered: None,
+        }
+    }
+
+    fn slot(&mut self, milestone: SetupMilestone) -> &mut Option<std::time::Instant> {
+        match milestone {
+            SetupMilestone::Authenticated => &mut self.authenticated,
+            SetupMilestone::Routed => &mut self.routed,
+            SetupMilestone::FirstProvisional => &mut self.first_provisional,
+            SetupMilestone::Answered => &mut self.answered,
+        }
+    }
+
+    /// Record `milestone` at `at` unless it was reached already
+    pub fn mark(&mut self, milestone: SetupMilestone, at: std::time::Instant) {
+        self.slot(milestone).get_or_insert(at);
+    }
+
+    /// Time from the INVITE to `milestone`, if reached
+    pub fn elapsed(&self, milestone: SetupMilestone) -> Option<Duration> {
+        let at = match milestone {
+            SetupMilestone::Authenticated => self.authenticated,
+            SetupMilestone::Routed => self.routed,
+            SetupMilestone::FirstProvisional => self.first_provisional,
+            SetupMilestone::Answered => self.answered,
+        }?;
+        Some(at.saturating_duration_since(self.invite_received))
+    }
+
+    /// [`elapsed`](Self::elapsed) in milliseconds, as kept in CDRs
+    pub fn millis(&self, milestone: SetupMilestone) -> Option<i32> {
+        self.elapsed(milestone)
+            .map(|elapsed| elapsed.as_millis().min(i32::MAX as u128) as i32)
+    }
+}
+
+/// Calls an SLO is evaluated over, one series each
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SloScope {
+    /// All calls together
+    #[default]
+    All,
+    /// The calls of each tenant
+    Tenant,
+    /// The calls of each trunk
+    Trunk,
+}
+
+/// Calls a series of samples covers
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize)]
+pub struct LatencySeries {
+    pub scope: SloScope,
+    /// Tenant or trunk; none for all calls
+    pub name: Option<String>,
+}
+
+impl LatencySeries {
+    pub fn all() -> Self {
+        Self {
+            scope: SloScope::All,
+            name: None,
+        }
+    }
+
+    pub fn tenant(tenant: &str) -> Self {
+        Self {
+            scope: SloScope::Tenant,
+            name: Some(tenant.to_string()),
+        }
+    }
+
+    pub fn trunk(trunk: &str) -> Self {
+        Self {
+            scope: SloScope::Trunk,
+            name: Some(trunk.to_string()),
+        }
+    }
+}
+
+impl std::fmt::Display for LatencySeries {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match (&self.scope, &self.name) {
+            (SloScope::Tenant, Some(name)) => write!(f, "tenant {}", name),
+            (SloScope::Trunk, Some(name)) => write!(f, "trunk {}", name),
+            _ => f.write_str("all calls"),
+        }
+    }
+}
+
+/// Latency objective of a milestone, e.g. 95% of calls ringing within
+/// 500 ms
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SloDefinition {
+    pub name: String,
+    pub milestone: SetupMilestone,
+    /// Percentile compared to the threshold, e.g. 95 or 99.9
+    pub percentile: f64,
+    pub threshold_ms: u64,
+    /// Calls considered: those set up within the last `window_secs`
+    #[serde(default = "default_window_secs")]
+    pub window_secs: u64,
+    /// Fewer calls in the window leave the SLO's state as it is
+    #[serde(default = "default_min_samples")]
+    pub min_samples: usize,
+    #[serde(default)]
+    pub per: SloScope,
+}
+
+fn default_window_secs() -> u64 {
+    300
+}
+
+fn default_min_samples() -> usize {
+    20
+}
+
+/// SLOs of call setup and where breaches are reported
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SetupLatencyConfig {
+    pub slos: Vec<SloDefinition>,
+    /// How often the SLOs are evaluated
+    pub evaluation_interval_secs: u64,
+    /// Breaches and recoveries kept for the API
+    pub history_size: usize,
+    /// `http://` URL breaches and recoveries are POSTed to
+    pub webhook_url: Option<String>,
+    pub webhook_timeout_ms: u64,
+}
+
+impl Default for SetupLatencyConfig {
+    fn default() -> Self {
+        Self {
+            slos: Vec::new(),
+            evaluation_interval_secs: 30,
+            history_size: 200,
+            webhook_url: None,
+            webhook_timeout_ms: 2000,
+        }
+    }
+}
+
+impl SetupLatencyConfig {
+    pub fn validate(&self) -> Result<(), String> {
+        if self.evaluation_interval_secs == 0 {
+            return Err("evaluation_interval_secs must be at least 1".to_string());
+        }
+        for (i, slo) in self.slos.iter().enumerate() {
+            if slo.name.trim().is_empty() {
+                return Err(format!("slos[{}] has no name", i));
+            }
+            if self.slos[..i].iter().any(|other| other.name == slo.name) {
+                return Err(format!("SLO {} is defined twice", slo.name));
+            }
+            if !(slo.percentile > 0.0 && slo.percentile <= 100.0) {
+                return Err(format!(
+                    "SLO {} percentile {} must be above 0 and at most 100",
+                    slo.name, slo.percentile
+                ));
+            }
+            if slo.threshold_ms == 0 || slo.window_secs == 0 {
+                return Err(format!(
+                    "SLO {} needs threshold_ms and window_secs above 0",
+                    slo.name
+                ));
+            }
+        }
+        if let Some(url) = &self.webhook_url {
+            WebhookTarget::parse(url)?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SloEventKind {
+    /// The percentile exceeded the threshold
+    Breached,
+    /// The percentile is back within the threshold
+    Recovered,
+}
+
+/// SLO breach or recovery, published to subscribers
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SloEvent {
+    pub kind: SloEventKind,
+    pub slo: String,
+    pub milestone: SetupMilestone,
+    pub series: LatencySeries,
+    pub percentile: f64,
+    pub threshold_ms: u64,
+    pub observed_ms: u64,
+    /// Calls in the window
+    pub samples: usize,
+    pub timestamp: DateTime<Utc>,
+}
+
+/// Latest evaluation of an SLO over one series
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SeriesStatus {
+    pub series: LatencySeries,
+    pub samples: usize,
+    /// Percentile over the window; none below `min_samples`
+    pub observed_ms: Option<u64>,
+    pub breached: bool,
+    /// Breached since
+    pub since: Option<DateTime<Utc>>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SloStatus {
+    #[serde(flatten)]
+    pub slo: SloDefinition,
+    pub series: Vec<SeriesStatus>,
+}
+
+/// Bucket of a latency histogram
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct HistogramBucket {
+    /// Upper bound; none for the overflow bucket
+    pub le_ms: Option<u64>,
+    pub count: usize,
+}
+
+/// Distribution of one milestone over a series, for the samples kept
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LatencyHistogram {
+    pub milestone: SetupMilestone,
+    pub series: LatencySeries,
+    pub count: usize,
+    pub buckets: Vec<HistogramBucket>,
+}
+
+/// SLOs, histograms and recent breaches
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SetupLatencyStatus {
+    pub slos: Vec<SloStatus>,
+    pub histograms: Vec<LatencyHistogram>,
+    /// Most recent first
+    pub history: Vec<SloEvent>,
+    pub timestamp: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone)]
+struct Evaluation {
+    samples: usize,
+    observed_ms: Option<u64>,
+    breached_since: Option<DateTime<Utc>>,
+}
+
+type SeriesKey = (SetupMilestone, LatencySeries);
+
+/// Files call setup times and evaluates the SLOs over them
+pub struct SetupLatencyMonitor {
+    config: SetupLatencyConfig,
+    webhook: Option<WebhookTarget>,
+    /// Time from the INVITE in milliseconds, with when it was filed
+    samples: Mutex<HashMap<SeriesKey, VecDeque<(Instant, u64)>>>,
+    /// Keyed by SLO name and series
+    evaluations: Mutex<HashMap<(String, LatencySeries), Evaluation>>,
+    history: Mutex<VecDeque<SloEvent>>,
+    events: broadcast::Sender<SloEvent>,
+    clock: Arc<dyn Clock>,
+}
+
+impl SetupLatencyMonitor {
+    pub fn new(config: SetupLatencyConfig) -> Self {
+        let (events, _) = broadcast::channel(64);
+        // Validated at startup; an invalid URL only loses the webhook
+        let webhook = config.webhook_url.as_deref().and_then(|url| {
+            WebhookTarget::parse(url)
+                .map_err(|e| warn!("SLO webhook disabled: {}", e))
+                .ok()
+        });
+        Self {
+            config,
+            webhook,
+            samples: Mutex::new(HashMap::new()),
+            evaluations: Mutex::new(HashMap::new()),
+            history: Mutex::new(VecDeque::new()),
+            events,
+            clock: system_clock(),
+        }
+    }
+
+    /// Time samples and windows by `clock`
+    pub fn with_clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    pub fn config(&self) -> &SetupLatencyConfig {
+        &self.config
+    }
+
+    /// Breaches and recoveries
+    pub fn subscribe(&self) -> broadcast::Receiver<SloEvent> {
+        self.events.subscribe()
+    }
+
+    /// File the milestones a call reached, under all calls, its tenant and
+    /// its trunk
+    pub fn record(&self, timeline: &SetupTimeline, tenant: Option<&str>, trunk: Option<&str>) {
+        for milestone in SetupMilestone::ALL {
+            if let Some(elapsed) = timeline.elapsed(milestone) {
+                self.record_sample(milestone, elapsed, tenant, trunk);
+            }
+        }
+    }
+
+    /// File the time from the INVITE to `milestone` of one call
+    pub fn record_sample(
+        &self,
+        milestone: SetupMilestone,
+        elapsed: Duration,
+        tenant: Option<&str>,
+        trunk: Option<&str>,
+    ) {
+        histogram!(
+            "call_setup_latency_seconds",
+            "milestone" => milestone.as_str(),
+            "tenant" => tenant.unwrap_or_default().to_string(),
+            "trunk" => trunk.unwrap_or_default().to_string()
+        )
+        .record(elapsed.as_secs_f64());
+
+        let now = self.clock.instant();
+        let millis = elapsed.as_millis().min(u64::MAX as u128) as u64;
+        let series = std::iter::once(LatencySeries::all())
+            .chain(tenant.map(LatencySeries::tenant))
+            .chain(trunk.map(LatencySeries::trunk));
+        let mut samples = self.samples.lock().unwrap();
+        for series in series {
+            let kept = samples.entry((milestone, series)).or_default();
+            if kept.len() >= MAX_SAMPLES {
+                kept.pop_front();
+            }
+            kept.push_back((now, millis));
+        }
+    }
+
+    /// Longest window of any SLO; samples older than it are dropped
+    fn retention(&self) -> Duration {
+        let longest = self
+            .config
+            .slos
+            .iter()
+            .map(|slo| slo.window_secs)
+            .max()
+            .unwrap_or_else(default_window_secs);
+        Duration::from_secs(longest)
+    }
+
+    /// Evaluate every SLO now; returns the breaches and recoveries found,
+    /// which are also published and kept in the history
+    pub fn evaluate(&self) -> Vec<SloEvent> {
+        let now = self.clock.instant();
+        let timestamp = self.clock.now();
+        let retention = self.retention();
+
+        let mut samples = self.samples.lock().unwrap();
+        samples.retain(|_, kept| {
+            while kept
+                .front()
+                .is_some_and(|(at, _)| now.saturating_duration_since(*at) > retention)
+            {
+                kept.pop_front();
+            }
+            !kept.is_empty()
+        });
+
+        let mut evaluations = self.evaluations.lock().unwrap();
+        let mut found = Vec::new();
+        for slo in &self.config.slos {
+            let window = Duration::from_secs(slo.window_secs);
+            for ((milestone, series), kept) in samples.iter() {
+                if *milestone != slo.milestone || series.scope != slo.per {
+                    continue;
+                }
+                let mut values: Vec<u64> = kept
+                    .iter()
+                    .filter(|(at, _)| now.saturating_duration_since(*at) <= window)
+                    .map(|(_, millis)| *millis)
+                    .collect();
+                let evaluation = evaluations
+                    .entry((slo.name.clone(), series.clone()))
+                    .or_insert(Evaluation {
+                        samples: 0,
+                        observed_ms: None,
+                        breached_since: None,
+                    });
+                evaluation.samples = values.len();
+                if values.len() < slo.min_samples.max(1) {
+                    evaluation.observed_ms = None;
+                    continue;
+                }
+                let observed_ms = percentile(&mut values, slo.percentile);
+                evaluation.observed_ms = Some(observed_ms);
+
+                let breached = observed_ms > slo.threshold_ms;
+                let kind = match (breached, evaluation.breached_since.is_some()) {
+                    (true, false) => {
+                        evaluation.breached_since = Some(timestamp);
+                        SloEventKind::Breached
+                    }
+                    (false, true) => {
+                        evaluation.breached_since = None;
+                        SloEventKind::Recovered
+                    }
+                    _ => continue,
+                };
+                found.push(SloEvent {
+                    kind,
+                    slo: slo.name.clone(),
+                    milestone: slo.milestone,
+                    series: series.clone(),
+                    percentile: slo.percentile,
+                    threshold_ms: slo.threshold_ms,
+                    observed_ms,
+                    samples: values.len(),
+                    timestamp,
+                });
+            }
+        }
+        drop(evaluations);
+        drop(samples);
+
+        found.sort_by(|a, b| (&a.slo, &a.series).cmp(&(&b.slo, &b.series)));
+        let mut history = self.history.lock().unwrap();
+        for event in &found {
+            match event.kind {
+                SloEventKind::Breached => warn!(
+                    "SLO {} breached for {}: p{} {} is {} ms (threshold {} ms, {} calls)",
+                    event.slo,
+                    event.series,
+                    event.percentile,
+                    event.milestone,
+                    event.observed_ms,
+                    event.threshold_ms,
+                    event.samples
+                ),
+                SloEventKind::Recovered => info!(
+                    "SLO {} recovered for {}: p{} {} is {} ms",
+                    event.slo, event.series, event.percentile, event.milestone, event.observed_ms
+                ),
+            }
+            history.push_front(event.clone());
+            let _ = self.events.send(event.clone());
+        }
+        history.truncate(self.config.history_size);
+        found
+    }
+
+    /// Latest evaluation of every SLO, histograms of the samples kept and
+    /// the breach history
+    pub fn status(&self) -> SetupLatencyStatus {
+        let evaluations = self.evaluations.lock().unwrap();
+        let slos = self
+            .config
+            .slos
+            .iter()
+            .map(|slo| {
+                let mut series: Vec<SeriesStatus> = evaluations
+                    .iter()
+                    .filter(|((name, _), _)| *name == slo.name)
+                    .map(|((_, series), evaluation)| SeriesStatus {
+                        series: series.clone(),
+                        samples: evaluation.samples,
+                        observed_ms: evaluation.observed_ms,
+                        breached: evaluation.breached_since.is_some(),
+                        since: evaluation.breached_since,
+                    })
+                    .collect();
+                series.sort_by(|a, b| a.series.cmp(&b.series));
+                SloStatus {
+                    slo: slo.clone(),
+                    series,
+                }
+            })
+            .collect();
+        drop(evaluations);
+
+        let mut histograms: Vec<LatencyHistogram> = self
+            .samples
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|((milestone, series), kept)| {
+                let mut buckets: Vec<HistogramBucket> = BUCKETS_MS
+                    .iter()
+                    .map(|le_ms| HistogramBucket {
+                        le_ms: Some(*le_ms),
+                        count: 0,
+                    })
+                    .chain(std::iter::once(HistogramBucket {
+                        le_ms: None,
+                        count: 0,
+                    }))
+                    .collect();
+                for (_, millis) in kept {
+                    let i = BUCKETS_MS
+                        .iter()
+                        .position(|le_ms| millis <= le_ms)
+                        .unwrap_or(BUCKETS_MS.len());
+                    buckets[i].count += 1;
+                }
+                LatencyHistogram {
+                    milestone: *milestone,
+                    series: series.clone(),
+                    count: kept.len(),
+                    buckets,
+                }
+            })
+            .collect();
+        histograms.sort_by(|a, b| (a.milestone, &a.series).cmp(&(b.milestone, &b.series)));
+
+        SetupLatencyStatus {
+            slos,
+            histograms,
+            history: self.history.lock().unwrap().iter().cloned().collect(),
+            timestamp: self.clock.now(),
+        }
+    }
+
+    /// Run [`evaluate`](Self::evaluate) every `evaluation_interval_secs`,
+    /// POSTing what it finds to the webhook
+    pub fn spawn_evaluator(self: Arc<Self>) -> JoinHandle<()> {
+        let period = Duration::from_secs(self.config.evaluation_interval_secs.max(1));
+        let timeout = Duration::from_millis(self.config.webhook_timeout_ms);
+        tokio::spawn(async move {
+            let mut ticker = tokio::time::interval(period);
+            loop {
+                ticker.tick().await;
+                let found = self.evaluate();
+                let Some(webhook) = &self.webhook else {
+                    continue;
+                };
+                for event in found {
+                    match tokio::time::timeout(timeout, webhook.post(&event)).await {
+                        Ok(Ok(())) => {}
+                        Ok(Err(e)) => warn!("SLO webhook failed: {}", e),
+                        Err(_) => warn!("SLO webhook timed out after {:?}", timeout),
+                    }
+                }
+            }
+        })
+    }
+}
+
+/// Nearest-rank percentile of `values`
+fn percentile(values: &mut [u64], percentile: f64) -> u64 {
+    values.sort_unstable();
+    let rank = (percentile / 100.0 * values.len() as f64).ceil() as usize;
+    values[rank.clamp(1, values.len()) - 1]
+}
+
+/// Where events are POSTed as JSON, e.g. SLO alerts
+#[derive(Debug, Clone)]
+pub(crate) struct WebhookTarget {
+    /// `host:port` connected to and sent as Host
+    authority: String,
+    path: String,
+}
+
+impl WebhookTarget {
+    pub(crate) fn parse(url: &str) -> Result<Self, String> {
+        let rest = url
+            .strip_prefix("http://")
+            .ok_or_else(|| format!("Unsupported webhook URL {} (only http:// is supported)", url))?;
+        let (authority, path) = match rest.find('/') {
+            Some(i) => (&rest[..i], &rest[i..]),
+            None => (rest, "/"),
+        };
+        if authority.is_empty() {
+            return Err(format!("Webhook URL {} has no host", url));
+        }
+        let has_port = match authority.rfind(']') {
+            Some(i) => authority[i..].contains(':'),
+            None => authority.contains(':'),
+        };
+        Ok(Self {
+            authority: if has_port {
+                authority.to_string()
+            } else {
+                format!("{}:80", authority)
+            },
+            path: path.to_string(),
+        })
+    }
+
+    pub(crate) async fn post(&self, event: &impl Serialize) -> Result<(), String> {
+        let body = serde_json::to_vec(event).map_err(|e| e.to_string())?;
+        // HTTP/1.0, so the answer is never chunked
+        let head = format!(
+            "POST {} HTTP/1.0\r\n\
+             Host: {}\r\n\
+             User-Agent: yakyak\r\n\
+             Content-Type: application/json\r\n\
+             Content-Length: {}\r\n\r\n",
+            self.path,
+            self.authority,
+            body.len()
+        );
+        let mut stream = TcpStream::connect(&self.authority)
+            .await
+            .map_err(|e| format!("Failed to connect to {}: {}", self.authority, e))?;
+        stream
+            .write_all(head.as_bytes())
+            .await
+            .map_err(|e| format!("Failed to send event: {}", e))?;
+        stream
+            .write_all(&body)
+            .await
+            .map_err(|e| format!("Failed to send event: {}", e))?;
+        // Only the status line matters
+        let mut answer = Vec::new();
+        (&mut stream)
+            .take(1024)
+            .read_to_end(&mut answer)
+            .await
+            .map_err(|e| format!("Failed to read answer: {}", e))?;
+        let status = String::from_utf8_lossy(&answer)
+            .lines()
+            .next()
+            .and_then(|line| line.split_whitespace().nth(1))
+            .and_then(|code| code.parse::<u16>().ok())
+            .ok_or_else(|| format!("Malformed answer from {}", self.authority))?;
+        if (200..300).contains(&status) {
+            Ok(())
+        } else {
+            Err(format!("{} answered {}", self.authority, status))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::ManualClock;
+
+    fn slo(per: SloScope) -> SloDefinition {
+        SloDefinition {
+            name: "ringing".to_string(),
+            milestone: SetupMilestone::FirstProvisional,
+            percentile: 95.0,
+            threshold_ms: 500,
+            window_secs: 60,
+            min_samples: 10,
+            per,
+        }
+    }
+
+    fn monitor(slos: Vec<SloDefinition>) -> (SetupLatencyMonitor, Arc<ManualClock>) {
+        let clock = Arc::new(ManualClock::new());
+        let config = SetupLatencyConfig {
+            slos,
+            ..Default::default()
+        };
+        (SetupLatencyMonitor::new(config).with_clock(clock.clone()), clock)
+    }
+
+    /// File `count` calls ringing after `millis`
+    fn feed(monitor: &SetupLatencyMonitor, count: usize, millis: u64, tenant: Option<&str>) {
+        for _ in 0..count {
+            monitor.record_sample(
+                SetupMilestone::FirstProvisional,
+                Duration::from_millis(millis),
+                tenant,
+                Some("carrier"),
+            );
+        }
+    }
+
+    #[test]
+    fn test_timeline_keeps_first_mark() {
+        let invite = std::time::Instant::now();
+        let mut timeline = SetupTimeline::starting_at(invite);
+        timeline.mark(SetupMilestone::FirstProvisional, invite + Duration::from_millis(120));
+        timeline.mark(SetupMilestone::FirstProvisional, invite + Duration::from_millis(900));
+
+        assert_eq!(timeline.millis(SetupMilestone::FirstProvisional), Some(120));
+        assert_eq!(timeline.millis(SetupMilestone::Answered), None);
+    }
+
+    #[test]
+    fn test_breach_and_recovery_at_threshold() {
+        let (monitor, clock) = monitor(vec![slo(SloScope::All)]);
+        let mut rx = monitor.subscribe();
+
+        // p95 of 90 fast and 10 slow calls is slow
+        feed(&monitor, 90, 200, None);
+        feed(&monitor, 10, 800, None);
+        let found = monitor.evaluate();
+        assert_eq!(found.len(), 1);
+        assert_eq!(found[0].kind, SloEventKind::Breached);
+        assert_eq!(found[0].observed_ms, 800);
+        assert_eq!(rx.try_recv().unwrap().kind, SloEventKind::Breached);
+
+        // Still breached: nothing new
+        assert!(monitor.evaluate().is_empty());
+
+        // The slow calls leave the window; exactly at the threshold is within
+        clock.advance(Duration::from_secs(61));
+        feed(&monitor, 100, 500, None);
+        let found = monitor.evaluate();
+        assert_eq!(found.len(), 1);
+        assert_eq!(found[0].kind, SloEventKind::Recovered);
+        assert_eq!(found[0].observed_ms, 500);
+
+        let status = monitor.status();
+        assert!(!status.slos[0].series[0].breached);
+        assert_eq!(status.history.len(), 2);
+        assert_eq!(status.history[0].kind, SloEventKind::Recovered);
+    }
+
+    #[test]
+    fn test_too_few_samples_keep_state() {
+        let (monitor, _) = monitor(vec![slo(SloScope::All)]);
+
+        feed(&monitor, 9, 5000, None);
+        assert!(monitor.evaluate().is_empty());
+        assert_eq!(monitor.status().slos[0].series[0].observed_ms, None);
+    }
+
+    #[test]
+    fn test_tenant_series_are_evaluated_apart() {
+        let (monitor, _) = monitor(vec![slo(SloScope::Tenant)]);
+
+        feed(&monitor, 20, 100, Some("acme.example.com"));
+        feed(&monitor, 20, 1500, Some("globex.example.com"));
+        let found = monitor.evaluate();
+        assert_eq!(found.len(), 1);
+        assert_eq!(found[0].series, LatencySeries::tenant("globex.example.com"));
+
+        let status = monitor.status();
+        assert_eq!(status.slos[0].series.len(), 2);
+        let histogram = status
+            .histograms
+            .iter()
+            .find(|h| h.series == LatencySeries::trunk("carrier"))
+            .unwrap();
+        assert_eq!(histogram.count, 40);
+        assert_eq!(histogram.buckets[1].count, 20);
+        assert_eq!(histogram.buckets[5].count, 20);
+    }
+
+    #[test]
+    fn test_config_validation() {
+        let mut config = SetupLatencyConfig {
+            slos: vec![slo(SloScope::All)],
+            ..Default::default()
+        };
+        assert!(config.validate().is_ok());
+
+        config.slos[0].percentile = 0.0;
+        assert!(config.validate().is_err());
+        config.slos[0].percentile = 99.9;
+        config.slos.push(slo(SloScope::Trunk));
+        assert!(config.validate().is_err());
+        config.slos.pop();
+
+        config.webhook_url = Some("https://alerts.example.com/slo".to_string());
+        assert!(config.validate().is_err());
+        config.webhook_url = Some("http://alerts.example.com/slo".to_string());
+        assert!(config.validate().is_ok());
+    }
+}
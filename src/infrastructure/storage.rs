@@ -0,0 +1,827 @@
+//! Disk space guardrails for recordings, voicemail, captures and archives
+//!
+//! [`StorageGuard`] checks a category's directory before anything starts
+//! writing to it: its filesystem must keep `min_free_mb` free and the
+//! directory must stay within its quota (voicemail also per mailbox).
+//! Refusals are [`StorageError`]s, published as `refused` events. A monitor
+//! task measures every category each `check_interval_secs` and publishes a
+//! `warning` when one crosses a threshold of its quota (of its filesystem
+//! when it has no quota), and `cleared` once it is back below them all.
+//!
+//! A category with the `oldest_first` cleanup policy has its oldest files
+//! deleted when it reaches its quota, down to the lowest threshold.
+//! Captures have that policy unless configured otherwise; nothing else is
+//! ever deleted unless configured.
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime};
+use tokio::sync::broadcast;
+use tokio::task::JoinHandle;
+use tracing::{info, warn};
+
+const MB: u64 = 1024 * 1024;
+
+/// What a directory holds
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum StorageCategory {
+    Recordings,
+    Voicemail,
+    /// Packet and debug captures
+    Captures,
+    /// CDR archives
+    Archives,
+}
+
+impl StorageCategory {
+    pub const ALL: [StorageCategory; 4] = [
+        StorageCategory::Recordings,
+        StorageCategory::Voicemail,
+        StorageCategory::Captures,
+        StorageCategory::Archives,
+    ];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            StorageCategory::Recordings => "recordings",
+            StorageCategory::Voicemail => "voicemail",
+            StorageCategory::Captures => "captures",
+            StorageCategory::Archives => "archives",
+        }
+    }
+}
+
+impl std::fmt::Display for StorageCategory {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// What happens to a category's files when it reaches its quota
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CleanupPolicy {
+    /// Refuse new files
+    Never,
+    /// Delete the oldest files
+    OldestFirst,
+}
+
+/// Directory and quota of one category
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct CategoryConfig {
+    /// Directory the category writes to; categories without one are not
+    /// guarded
+    pub dir: Option<PathBuf>,
+    pub quota_mb: Option<u64>,
+    /// `oldest_first` for captures, `never` for the others when unset
+    pub cleanup: Option<CleanupPolicy>,
+}
+
+/// Quotas, free-space floor and warning thresholds
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct StorageConfig {
+    /// Space every guarded filesystem must keep free
+    pub min_free_mb: u64,
+    /// How often the monitor measures usage
+    pub check_interval_secs: u64,
+    /// Percentages of a quota (or filesystem) that raise a warning,
+    /// ascending
+    pub warning_thresholds: Vec<u8>,
+    pub recordings: CategoryConfig,
+    /// Voicemail base directory, with a directory per mailbox
+    pub voicemail: CategoryConfig,
+    /// Quota of each mailbox
+    pub mailbox_quota_mb: Option<u64>,
+    pub captures: CategoryConfig,
+    pub archives: CategoryConfig,
+}
+
+impl Default for StorageConfig {
+    fn default() -> Self {
+        Self {
+            min_free_mb: 1024,
+            check_interval_secs: 60,
+            warning_thresholds: vec![80, 90, 95],
+            recordings: CategoryConfig::default(),
+            voicemail: CategoryConfig::default(),
+            mailbox_quota_mb: None,
+            captures: CategoryConfig::default(),
+            archives: CategoryConfig::default(),
+        }
+    }
+}
+
+impl StorageConfig {
+    pub fn validate(&self) -> Result<(), String> {
+        if self.check_interval_secs == 0 {
+            return Err("check_interval_secs must be at least 1".to_string());
+        }
+        if self.warning_thresholds.iter().any(|t| !(1..=100).contains(t))
+            || self.warning_thresholds.windows(2).any(|w| w[0] >= w[1])
+        {
+            return Err(format!(
+                "warning_thresholds {:?} must be ascending percentages between 1 and 100",
+                self.warning_thresholds
+            ));
+        }
+        for category in StorageCategory::ALL {
+            if self.category(category).quota_mb == Some(0) {
+                return Err(format!("{} quota_mb must be above 0", category));
+            }
+        }
+        if self.mailbox_quota_mb == Some(0) {
+            return Err("mailbox_quota_mb must be above 0".to_string());
+        }
+        Ok(())
+    }
+
+    pub fn category(&self, category: StorageCategory) -> &CategoryConfig {
+        match category {
+            StorageCategory::Recordings => &self.recordings,
+            StorageCategory::Voicemail => &self.voicemail,
+            StorageCategory::Captures => &self.captures,
+            StorageCategory::Archives => &self.archives,
+        }
+    }
+
+    pub fn cleanup_policy(&self, category: StorageCategory) -> CleanupPolicy {
+        self.category(category).cleanup.unwrap_or(match category {
+            StorageCategory::Captures => CleanupPolicy::OldestFirst,
+            _ => CleanupPolicy::Never,
+        })
+    }
+}
+
+/// Size and free space of a filesystem
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DiskSpace {
+    pub total_bytes: u64,
+    /// Space available to unprivileged writers
+    pub available_bytes: u64,
+}
+
+/// A file under a guarded directory
+#[derive(Debug, Clone)]
+pub struct StoredFile {
+    pub path: PathBuf,
+    pub size_bytes: u64,
+    pub modified: SystemTime,
+}
+
+/// Filesystem measurements the guard works from
+pub trait FsStats: Send + Sync {
+    /// Size and free space of the filesystem `path` is (or would be) on
+    fn disk_space(&self, path: &Path) -> io::Result<DiskSpace>;
+
+    /// Files under `path`, recursively; none when it does not exist
+    fn files(&self, path: &Path) -> io::Result<Vec<StoredFile>>;
+
+    fn remove_file(&self, path: &Path) -> io::Result<()>;
+
+    /// Bytes of the files under `path`
+    fn dir_usage(&self, path: &Path) -> io::Result<u64> {
+        Ok(self.files(path)?.iter().map(|f| f.size_bytes).sum())
+    }
+}
+
+/// The local filesystem
+pub struct SystemFs;
+
+impl FsStats for SystemFs {
+    fn disk_space(&self, path: &Path) -> io::Result<DiskSpace> {
+        // A directory not created yet will be on its nearest ancestor's
+        let existing = path
+            .ancestors()
+            .find(|p| p.exists())
+            .unwrap_or_else(|| Path::new("."));
+        statvfs(existing)
+    }
+
+    fn files(&self, path: &Path) -> io::Result<Vec<StoredFile>> {
+        let mut files = Vec::new();
+        let mut dirs = vec![path.to_path_buf()];
+        while let Some(dir) = dirs.pop() {
+            let entries = match fs::read_dir(&dir) {
+                Ok(entries) => entries,
+                Err(e) if e.kind() == io::ErrorKind::NotFound => continue,
+                Err(e) => return Err(e),
+            };
+            for entry in entries {
+                let entry = entry?;
+                let metadata = entry.metadata()?;
+                if metadata.is_dir() {
+                    dirs.push(entry.path());
+                } else if metadata.is_file() {
+                    files.push(StoredFile {
+                        path: entry.path(),
+                        size_bytes: metadata.len(),
+                        modified: metadata.modified().unwrap_or(SystemTime::UNIX_EPOCH),
+                    });
+                }
+            }
+        }
+        Ok(files)
+    }
+
+    fn remove_file(&self, path: &Path) -> io::Result<()> {
+        fs::remove_file(path)
+    }
+}
+
+#[cfg(unix)]
+fn statvfs(path: &Path) -> io::Result<DiskSpace> {
+    use std::ffi::CString;
+    use std::os::unix::ffi::OsStrExt;
+
+    let c_path = CString::new(path.as_os_str().as_bytes())
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+    // SAFETY: c_path is NUL-terminated and stats is written by statvfs
+    // before it is read
+    let mut stats: libc::statvfs = unsafe { std::mem::zeroed() };
+    if unsafe { libc::statvfs(c_path.as_ptr(), &mut stats) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    let fragment = stats.f_frsize as u64;
+    Ok(DiskSpace {
+        total_bytes: stats.f_blocks as u64 * fragment,
+        available_bytes: stats.f_bavail as u64 * fragment,
+    })
+}
+
+#[cfg(not(unix))]
+fn statvfs(_path: &Path) -> io::Result<DiskSpace> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "free space is only measured on Unix",
+    ))
+}
+
+/// Why something may not be written
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum StorageError {
+    /// The filesystem is below the free-space floor
+    LowDiskSpace {
+        category: StorageCategory,
+        available_mb: u64,
+        min_free_mb: u64,
+    },
+    QuotaExceeded {
+        category: StorageCategory,
+        used_mb: u64,
+        quota_mb: u64,
+    },
+    MailboxFull {
+        mailbox: String,
+        used_mb: u64,
+        quota_mb: u64,
+    },
+}
+
+impl StorageError {
+    pub fn category(&self) -> StorageCategory {
+        match self {
+            StorageError::LowDiskSpace { category, .. }
+            | StorageError::QuotaExceeded { category, .. } => *category,
+            StorageError::MailboxFull { .. } => StorageCategory::Voicemail,
+        }
+    }
+}
+
+impl std::fmt::Display for StorageError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            StorageError::LowDiskSpace {
+                category,
+                available_mb,
+                min_free_mb,
+            } => write!(
+                f,
+                "Not enough disk space for {}: {} MB free, {} MB must stay free",
+                category, available_mb, min_free_mb
+            ),
+            StorageError::QuotaExceeded {
+                category,
+                used_mb,
+                quota_mb,
+            } => write!(
+                f,
+                "Storage quota of {} exhausted: {} of {} MB used",
+                category, used_mb, quota_mb
+            ),
+            StorageError::MailboxFull {
+                mailbox,
+                used_mb,
+                quota_mb,
+            } => write!(
+                f,
+                "Mailbox {} is full: {} of {} MB used",
+                mailbox, used_mb, quota_mb
+            ),
+        }
+    }
+}
+
+impl std::error::Error for StorageError {}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum StorageEventKind {
+    /// Usage crossed a warning threshold upwards
+    Warning,
+    /// Usage dropped below every threshold
+    Cleared,
+    /// A recording, capture or message was not started
+    Refused,
+    /// Old files were deleted to stay within a quota
+    Cleanup,
+}
+
+/// Storage alert, published to subscribers
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct StorageEvent {
+    pub kind: StorageEventKind,
+    pub category: StorageCategory,
+    /// Threshold the category is at, in percent
+    pub threshold: Option<u8>,
+    pub message: String,
+    pub timestamp: DateTime<Utc>,
+}
+
+/// Usage of one category
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CategoryUsage {
+    pub category: StorageCategory,
+    pub dir: Option<PathBuf>,
+    pub used_bytes: u64,
+    pub quota_bytes: Option<u64>,
+    /// Filesystem the directory is on
+    pub disk: Option<DiskSpace>,
+    /// Share of the quota used, or of the filesystem without quota
+    pub percent_used: Option<f64>,
+    /// Highest warning threshold reached
+    pub threshold: Option<u8>,
+    pub cleanup: CleanupPolicy,
+}
+
+/// Usage of every category
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct StorageUsage {
+    pub min_free_bytes: u64,
+    pub categories: Vec<CategoryUsage>,
+    pub timestamp: DateTime<Utc>,
+}
+
+/// Checks quotas and free space before writes, and watches usage
+pub struct StorageGuard {
+    config: StorageConfig,
+    fs: Arc<dyn FsStats>,
+    /// Warning threshold each category was last measured at
+    levels: Mutex<HashMap<StorageCategory, u8>>,
+    events: broadcast::Sender<StorageEvent>,
+}
+
+impl StorageGuard {
+    pub fn new(config: StorageConfig) -> Self {
+        Self::with_fs(config, Arc::new(SystemFs))
+    }
+
+    pub fn with_fs(config: StorageConfig, fs: Arc<dyn FsStats>) -> Self {
+        let (events, _) = broadcast::channel(64);
+        Self {
+            config,
+            fs,
+            levels: Mutex::new(HashMap::new()),
+            events,
+        }
+    }
+
+    pub fn config(&self) -> &StorageConfig {
+        &self.config
+    }
+
+    /// Warnings, refusals and cleanups
+    pub fn subscribe(&self) -> broadcast::Receiver<StorageEvent> {
+        self.events.subscribe()
+    }
+
+    /// Whether a new file of `category` may be started
+    pub fn check(&self, category: StorageCategory) -> Result<(), StorageError> {
+        let settings = self.config.category(category);
+        let Some(dir) = settings.dir.as_deref() else {
+            return Ok(());
+        };
+
+        match self.fs.disk_space(dir) {
+            Ok(space) if space.available_bytes < self.config.min_free_mb * MB => {
+                return Err(self.refuse(StorageError::LowDiskSpace {
+                    category,
+                    available_mb: space.available_bytes / MB,
+                    min_free_mb: self.config.min_free_mb,
+                }));
+            }
+            Ok(_) => {}
+            Err(e) => warn!("Cannot measure free space of {}: {}", dir.display(), e),
+        }
+
+        if let Some(quota_mb) = settings.quota_mb {
+            let mut used = self.used_bytes(dir);
+            if used >= quota_mb * MB && self.cleanup(category) > 0 {
+                used = self.used_bytes(dir);
+            }
+            if used >= quota_mb * MB {
+                return Err(self.refuse(StorageError::QuotaExceeded {
+                    category,
+                    used_mb: used / MB,
+                    quota_mb,
+                }));
+            }
+        }
+        Ok(())
+    }
+
+    /// Whether a message may be recorded into a mailbox, stored in
+    /// `mailbox_dir`
+    pub fn check_mailbox(&self, mailbox: &str, mailbox_dir: &Path) -> Result<(), StorageError> {
+        self.check(StorageCategory::Voicemail)?;
+        if let Some(quota_mb) = self.config.mailbox_quota_mb {
+            let used = self.used_bytes(mailbox_dir);
+            if used >= quota_mb * MB {
+                return Err(self.refuse(StorageError::MailboxFull {
+                    mailbox: mailbox.to_string(),
+                    used_mb: used / MB,
+                    quota_mb,
+                }));
+            }
+        }
+        Ok(())
+    }
+
+    /// Delete the oldest files of `category` down to the lowest warning
+    /// threshold of its quota, if its policy allows; returns how many
+    pub fn cleanup(&self, category: StorageCategory) -> usize {
+        let settings = self.config.category(category);
+        let (Some(dir), Some(quota_mb)) = (settings.dir.as_deref(), settings.quota_mb) else {
+            return 0;
+        };
+        if self.config.cleanup_policy(category) != CleanupPolicy::OldestFirst {
+            return 0;
+        }
+
+        let mut files = match self.fs.files(dir) {
+            Ok(files) => files,
+            Err(e) => {
+                warn!("Cannot list {} for cleanup: {}", dir.display(), e);
+                return 0;
+            }
+        };
+        let target_percent = self.config.warning_thresholds.first().copied().unwrap_or(100);
+        let target = quota_mb * MB * u64::from(target_percent) / 100;
+        let mut used: u64 = files.iter().map(|f| f.size_bytes).sum();
+        if used < quota_mb * MB {
+            return 0;
+        }
+
+        files.sort_by_key(|f| f.modified);
+        let mut removed = 0;
+        let mut freed = 0;
+        for file in files {
+            if used <= target {
+                break;
+            }
+            match self.fs.remove_file(&file.path) {
+                Ok(()) => {
+                    used -= file.size_bytes;
+                    freed += file.size_bytes;
+                    removed += 1;
+                }
+                Err(e) => warn!("Cannot delete {}: {}", file.path.display(), e),
+            }
+        }
+
+        if removed > 0 {
+            let message = format!(
+                "Deleted {} oldest {} files ({} MB)",
+                removed,
+                category,
+                freed / MB
+            );
+            info!("{}", message);
+            self.publish(StorageEventKind::Cleanup, category, None, message);
+        }
+        removed
+    }
+
+    /// Current usage of every category
+    pub fn usage(&self) -> StorageUsage {
+        let levels = self.levels.lock().unwrap().clone();
+        StorageUsage {
+            min_free_bytes: self.config.min_free_mb * MB,
+            categories: StorageCategory::ALL
+                .into_iter()
+                .map(|category| {
+                    let mut usage = self.measure(category);
+                    usage.threshold = levels.get(&category).copied();
+                    usage
+                })
+                .collect(),
+            timestamp: Utc::now(),
+        }
+    }
+
+    /// Measure every category, clean up the ones over quota and publish
+    /// threshold changes
+    pub fn sweep(&self) {
+        for category in StorageCategory::ALL {
+            if self.config.category(category).dir.is_none() {
+                continue;
+            }
+            let mut usage = self.measure(category);
+            let over_quota = matches!(usage.quota_bytes, Some(quota) if usage.used_bytes >= quota);
+            if over_quota && self.cleanup(category) > 0 {
+                usage = self.measure(category);
+            }
+
+            let level = usage.percent_used.and_then(|percent| {
+                self.config
+                    .warning_thresholds
+                    .iter()
+                    .rev()
+                    .find(|t| percent >= f64::from(**t))
+                    .copied()
+            });
+            let previous = {
+                let mut levels = self.levels.lock().unwrap();
+                match level {
+                    Some(level) => levels.insert(category, level),
+                    None => levels.remove(&category),
+                }
+            };
+
+            match (previous, level) {
+                (previous, Some(level)) if previous.is_none_or(|p| level > p) => {
+                    let message = format!(
+                        "Storage of {} at {:.0}% (threshold {}%)",
+                        category,
+                        usage.percent_used.unwrap_or_default(),
+                        level
+                    );
+                    warn!("{}", message);
+                    self.publish(StorageEventKind::Warning, category, Some(level), message);
+                }
+                (Some(_), None) => {
+                    let message = format!("Storage of {} back below every threshold", category);
+                    info!("{}", message);
+                    self.publish(StorageEventKind::Cleared, category, None, message);
+                }
+                _ => {}
+            }
+        }
+    }
+
+    /// Run [`sweep`](Self::sweep) every `check_interval_secs`
+    pub fn spawn_monitor(self: Arc<Self>) -> JoinHandle<()> {
+        let period = Duration::from_secs(self.config.check_interval_secs.max(1));
+        tokio::spawn(async move {
+            let mut ticker = tokio::time::interval(period);
+            loop {
+                ticker.tick().await;
+                // Walking the directories blocks
+                let guard = self.clone();
+                if let Err(e) = tokio::task::spawn_blocking(move || guard.sweep()).await {
+                    warn!("Storage check failed: {}", e);
+                }
+            }
+        })
+    }
+
+    fn measure(&self, category: StorageCategory) -> CategoryUsage {
+        let settings = self.config.category(category);
+        let quota_bytes = settings.quota_mb.map(|quota| quota * MB);
+        let mut usage = CategoryUsage {
+            category,
+            dir: settings.dir.clone(),
+            used_bytes: 0,
+            quota_bytes,
+            disk: None,
+            percent_used: None,
+            threshold: None,
+            cleanup: self.config.cleanup_policy(category),
+        };
+        let Some(dir) = settings.dir.as_deref() else {
+            return usage;
+        };
+
+        usage.used_bytes = self.used_bytes(dir);
+        usage.disk = self.fs.disk_space(dir).ok();
+        usage.percent_used = match (quota_bytes, usage.disk) {
+            (Some(quota), _) => Some(usage.used_bytes as f64 * 100.0 / quota as f64),
+            (None, Some(disk)) if disk.total_bytes > 0 => Some(
+                disk.total_bytes.saturating_sub(disk.available_bytes) as f64 * 100.0
+                    / disk.total_bytes as f64,
+            ),
+            _ => None,
+        };
+        usage
+    }
+
+    fn used_bytes(&self, dir: &Path) -> u64 {
+        self.fs.dir_usage(dir).unwrap_or_else(|e| {
+            warn!("Cannot measure usage of {}: {}", dir.display(), e);
+            0
+        })
+    }
+
+    fn refuse(&self, error: StorageError) -> StorageError {
+        warn!("{}", error);
+        self.publish(
+            StorageEventKind::Refused,
+            error.category(),
+            None,
+            error.to_string(),
+        );
+        error
+    }
+
+    fn publish(
+        &self,
+        kind: StorageEventKind,
+        category: StorageCategory,
+        threshold: Option<u8>,
+        message: String,
+    ) {
+        let _ = self.events.send(StorageEvent {
+            kind,
+            category,
+            threshold,
+            message,
+            timestamp: Utc::now(),
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::FakeFs;
+
+    fn guard(config: StorageConfig, fs: &Arc<FakeFs>) -> StorageGuard {
+        StorageGuard::with_fs(config, fs.clone())
+    }
+
+    fn category(dir: &str, quota_mb: Option<u64>) -> CategoryConfig {
+        CategoryConfig {
+            dir: Some(PathBuf::from(dir)),
+            quota_mb,
+            cleanup: None,
+        }
+    }
+
+    #[test]
+    fn test_low_disk_space_refuses_and_publishes() {
+        let fs = Arc::new(FakeFs::new(100 * MB, 512 * MB));
+        let guard = guard(
+            StorageConfig {
+                recordings: category("/rec", None),
+                ..Default::default()
+            },
+            &fs,
+        );
+        let mut events = guard.subscribe();
+
+        let error = guard.check(StorageCategory::Recordings).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "Not enough disk space for recordings: 512 MB free, 1024 MB must stay free"
+        );
+        let event = events.try_recv().unwrap();
+        assert_eq!(event.kind, StorageEventKind::Refused);
+        assert_eq!(event.category, StorageCategory::Recordings);
+
+        // Unconfigured categories are not guarded
+        assert!(guard.check(StorageCategory::Archives).is_ok());
+        fs.set_available(2048 * MB);
+        assert!(guard.check(StorageCategory::Recordings).is_ok());
+    }
+
+    #[test]
+    fn test_cleanup_only_where_configured() {
+        let fs = Arc::new(FakeFs::new(100 * 1024 * MB, 50 * 1024 * MB));
+        for (i, name) in ["a", "b", "c", "d", "e"].iter().enumerate() {
+            fs.add_file(&format!("/cap/{}.pcap", name), 2 * MB, i as u64);
+            fs.add_file(&format!("/rec/{}.wav", name), 2 * MB, i as u64);
+        }
+        let guard = guard(
+            StorageConfig {
+                recordings: category("/rec", Some(10)),
+                captures: category("/cap", Some(10)),
+                ..Default::default()
+            },
+            &fs,
+        );
+
+        // Recordings are never deleted unless configured
+        assert!(matches!(
+            guard.check(StorageCategory::Recordings),
+            Err(StorageError::QuotaExceeded { used_mb: 10, quota_mb: 10, .. })
+        ));
+        assert_eq!(fs.file_count("/rec"), 5);
+
+        // Captures lose their oldest files down to 80% of the quota
+        assert!(guard.check(StorageCategory::Captures).is_ok());
+        assert!(!fs.exists("/cap/a.pcap"));
+        assert!(fs.exists("/cap/b.pcap"));
+        assert_eq!(fs.file_count("/cap"), 4);
+    }
+
+    #[test]
+    fn test_thresholds_warn_once_and_clear() {
+        let fs = Arc::new(FakeFs::new(100 * 1024 * MB, 50 * 1024 * MB));
+        fs.add_file("/rec/1.wav", 81 * MB, 1);
+        let guard = guard(
+            StorageConfig {
+                recordings: category("/rec", Some(100)),
+                ..Default::default()
+            },
+            &fs,
+        );
+        let mut events = guard.subscribe();
+
+        guard.sweep();
+        let event = events.try_recv().unwrap();
+        assert_eq!(event.kind, StorageEventKind::Warning);
+        assert_eq!(event.threshold, Some(80));
+
+        guard.sweep();
+        assert!(events.try_recv().is_err());
+
+        fs.add_file("/rec/2.wav", 10 * MB, 2);
+        guard.sweep();
+        assert_eq!(events.try_recv().unwrap().threshold, Some(90));
+        assert_eq!(guard.usage().categories[0].threshold, Some(90));
+
+        fs.remove_file(Path::new("/rec/1.wav")).unwrap();
+        guard.sweep();
+        assert_eq!(events.try_recv().unwrap().kind, StorageEventKind::Cleared);
+    }
+
+    #[test]
+    fn test_mailbox_quota() {
+        let fs = Arc::new(FakeFs::new(100 * 1024 * MB, 50 * 1024 * MB));
+        fs.add_file("/vm/1001/msg_1.wav", 5 * MB, 1);
+        let guard = guard(
+            StorageConfig {
+                voicemail: category("/vm", None),
+                mailbox_quota_mb: Some(5),
+                ..Default::default()
+            },
+            &fs,
+        );
+
+        assert_eq!(
+            guard.check_mailbox("1001", Path::new("/vm/1001")),
+            Err(StorageError::MailboxFull {
+                mailbox: "1001".to_string(),
+                used_mb: 5,
+                quota_mb: 5,
+            })
+        );
+        assert!(guard.check_mailbox("1002", Path::new("/vm/1002")).is_ok());
+    }
+
+    #[test]
+    fn test_config_validation() {
+        assert!(StorageConfig::default().validate().is_ok());
+        assert!(StorageConfig {
+            warning_thresholds: vec![90, 80],
+            ..Default::default()
+        }
+        .validate()
+        .is_err());
+
+        let config: StorageConfig = toml::from_str(
+            r#"
+            [captures]
+            dir = "/var/lib/yakyak/captures"
+            quota_mb = 2048
+            "#,
+        )
+        .unwrap();
+        assert_eq!(
+            config.cleanup_policy(StorageCategory::Captures),
+            CleanupPolicy::OldestFirst
+        );
+        assert_eq!(
+            config.cleanup_policy(StorageCategory::Recordings),
+            CleanupPolicy::Never
+        );
+    }
+}
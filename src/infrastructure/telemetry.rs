@@ -0,0 +1,603 @@
+//! OpenTelemetry tracing
+//!
+//! With `telemetry.enabled`, spans are exported over OTLP/gRPC to the
+//! collector at `telemetry.endpoint`. Every call gets a `call` span with its
+//! Call-ID, tenant and trunk; the SIP server opens a `sip` span per received
+//! message inside it, and the call's state changes (ringing, answered,
+//! held, resumed, terminated) are span events on it. REST requests continue
+//! the trace of their `traceparent` header and outbound HTTP requests carry
+//! one, so actions elsewhere join the calls they cause.
+//!
+//! Finished spans reach the exporter through a bounded queue: when the
+//! collector is slow or down, spans are dropped and counted rather than
+//! holding up call processing.
+
+use crate::application::events::EventBus;
+use crate::domain::call::CallEvent;
+use crate::domain::tenant_branding::realm_of;
+use crate::infrastructure::protocols::sip::address::uri_from_header;
+use crate::infrastructure::protocols::sip::{CallRouter, SipMessage, SipMethod, SipRequest};
+use futures::future::BoxFuture;
+use metrics::counter;
+use opentelemetry::propagation::TextMapPropagator;
+use opentelemetry::trace::{TraceContextExt, TraceResult, TracerProvider as _};
+use opentelemetry::{Context, KeyValue};
+use opentelemetry_otlp::WithExportConfig;
+use opentelemetry_sdk::export::trace::{ExportResult, SpanData, SpanExporter};
+use opentelemetry_sdk::propagation::TraceContextPropagator;
+use opentelemetry_sdk::trace::{Sampler, Span as SdkSpan, SpanProcessor, TracerProvider};
+use opentelemetry_sdk::Resource;
+use rsip::headers::UntypedHeader;
+use rsip::Header;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::sync::{broadcast, mpsc};
+use tokio::task::JoinHandle;
+use tracing::{debug, field, info, info_span, warn, Span, Subscriber};
+use tracing_opentelemetry::OpenTelemetrySpanExt;
+use tracing_subscriber::filter::{LevelFilter, Targets};
+use tracing_subscriber::registry::LookupSpan;
+use tracing_subscriber::Layer;
+
+/// Trace export settings
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct TelemetryConfig {
+    pub enabled: bool,
+    /// OTLP/gRPC endpoint of the collector
+    pub endpoint: String,
+    /// `service.name` of the exported spans
+    pub service_name: String,
+    /// Fraction of new traces recorded; traces continued from a sampled
+    /// parent are always recorded
+    pub sampling_ratio: f64,
+    /// Finished spans waiting for export; more are dropped
+    pub queue_size: usize,
+    /// Spans per export request
+    pub batch_size: usize,
+    pub export_timeout_secs: u64,
+}
+
+impl Default for TelemetryConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            endpoint: "http://localhost:4317".to_string(),
+            service_name: "yakyak".to_string(),
+            sampling_ratio: 1.0,
+            queue_size: 2048,
+            batch_size: 512,
+            export_timeout_secs: 10,
+        }
+    }
+}
+
+impl TelemetryConfig {
+    pub fn validate(&self) -> Result<(), String> {
+        if !(0.0..=1.0).contains(&self.sampling_ratio) {
+            return Err(format!(
+                "Sampling ratio {} must be between 0 and 1",
+                self.sampling_ratio
+            ));
+        }
+        if self.queue_size == 0 || self.batch_size == 0 {
+            return Err("Queue and batch size must be at least 1".to_string());
+        }
+        if self.enabled && self.endpoint.is_empty() {
+            return Err("Trace export needs a collector endpoint".to_string());
+        }
+        Ok(())
+    }
+}
+
+/// Tracer provider exporting to the configured collector
+///
+/// Must be called within the Tokio runtime, which runs the export.
+pub fn tracer_provider(config: &TelemetryConfig) -> Result<TracerProvider, String> {
+    let exporter = opentelemetry_otlp::SpanExporter::builder()
+        .with_tonic()
+        .with_endpoint(config.endpoint.clone())
+        .with_timeout(Duration::from_secs(config.export_timeout_secs))
+        .build()
+        .map_err(|e| format!("Failed to create OTLP exporter: {}", e))?;
+
+    Ok(TracerProvider::builder()
+        .with_span_processor(BoundedSpanProcessor::new(
+            exporter,
+            config.queue_size,
+            config.batch_size,
+        ))
+        .with_sampler(Sampler::ParentBased(Box::new(Sampler::TraceIdRatioBased(
+            config.sampling_ratio,
+        ))))
+        .with_resource(Resource::new([KeyValue::new(
+            "service.name",
+            config.service_name.clone(),
+        )]))
+        .build())
+}
+
+/// Tracing layer turning our spans into OpenTelemetry spans
+///
+/// Only spans and events of this crate are exported, so the exporter's own
+/// instrumentation (gRPC, HTTP) never feeds back into it.
+pub fn layer<S>(provider: &TracerProvider, service_name: &str) -> impl Layer<S>
+where
+    S: Subscriber + for<'span> LookupSpan<'span>,
+{
+    tracing_opentelemetry::layer()
+        .with_tracer(provider.tracer(service_name.to_string()))
+        .with_filter(Targets::new().with_target("yakyak", LevelFilter::INFO))
+}
+
+/// Hands finished spans to an exporter through a bounded queue
+///
+/// Spans arriving while the queue is full are dropped
+/// (`telemetry_spans_dropped_total`), failed exports are counted
+/// (`telemetry_export_failures_total`); neither ever blocks the caller.
+#[derive(Debug)]
+pub struct BoundedSpanProcessor {
+    queue: Mutex<Option<mpsc::Sender<SpanData>>>,
+}
+
+impl BoundedSpanProcessor {
+    pub fn new<E: SpanExporter + 'static>(mut exporter: E, queue_size: usize, batch_size: usize) -> Self {
+        let batch_size = batch_size.max(1);
+        let (tx, mut rx) = mpsc::channel(queue_size.max(1));
+        tokio::spawn(async move {
+            while let Some(span) = rx.recv().await {
+                // Whatever else is queued goes out in the same request
+                let mut batch = vec![span];
+                while batch.len() < batch_size {
+                    match rx.try_recv() {
+                        Ok(span) => batch.push(span),
+                        Err(_) => break,
+                    }
+                }
+                let export: BoxFuture<'static, ExportResult> = exporter.export(batch);
+                if let Err(e) = export.await {
+                    counter!("telemetry_export_failures_total").increment(1);
+                    debug!("Span export failed: {}", e);
+                }
+            }
+            exporter.shutdown();
+        });
+        Self {
+            queue: Mutex::new(Some(tx)),
+        }
+    }
+}
+
+impl SpanProcessor for BoundedSpanProcessor {
+    fn on_start(&self, _span: &mut SdkSpan, _cx: &Context) {}
+
+    fn on_end(&self, span: SpanData) {
+        if !span.span_context.is_sampled() {
+            return;
+        }
+        let queue = self.queue.lock().unwrap();
+        let Some(queue) = queue.as_ref() else {
+            return;
+        };
+        if queue.try_send(span).is_err() {
+            counter!("telemetry_spans_dropped_total").increment(1);
+        }
+    }
+
+    fn force_flush(&self) -> TraceResult<()> {
+        // Export runs on its own; nothing is held back here
+        Ok(())
+    }
+
+    fn shutdown(&self) -> TraceResult<()> {
+        // The export task sends what is queued, then ends
+        self.queue.lock().unwrap().take();
+        Ok(())
+    }
+}
+
+/// `traceparent` header value continuing `span`'s trace, `None` when the
+/// span is not exported
+pub fn traceparent(span: &Span) -> Option<String> {
+    let cx = span.context();
+    if !cx.span().span_context().is_valid() {
+        return None;
+    }
+    let mut headers = HashMap::new();
+    TraceContextPropagator::new().inject_context(&cx, &mut headers);
+    headers.remove("traceparent")
+}
+
+/// Make `span` a child of the remote span of a `traceparent` (and
+/// `tracestate`) header; an invalid header leaves it alone
+pub fn set_remote_parent(span: &Span, traceparent: &str, tracestate: Option<&str>) {
+    let mut headers = HashMap::new();
+    headers.insert("traceparent".to_string(), traceparent.to_string());
+    if let Some(tracestate) = tracestate {
+        headers.insert("tracestate".to_string(), tracestate.to_string());
+    }
+    let cx = TraceContextPropagator::new().extract(&headers);
+    if cx.span().span_context().is_remote() {
+        span.set_parent(cx);
+    }
+}
+
+/// Span of a call and whether the call got past its initial INVITE
+struct CallSpan {
+    span: Span,
+    established: bool,
+}
+
+/// The `call` spans of calls in progress
+///
+/// SIP messages of a call are traced inside its span; the span ends with
+/// the call, or right after its initial INVITE when that left no call
+/// behind (e.g. rejected before routing).
+pub struct CallSpans {
+    calls: Mutex<HashMap<String, CallSpan>>,
+    /// Tells refused INVITEs from calls
+    call_router: Option<Arc<CallRouter>>,
+}
+
+impl CallSpans {
+    pub fn new() -> Self {
+        Self {
+            calls: Mutex::new(HashMap::new()),
+            call_router: None,
+        }
+    }
+
+    /// End the span of an initial INVITE that created no call as soon as
+    /// it is handled (without, it ends with the call's events only)
+    pub fn with_call_router(mut self, call_router: Arc<CallRouter>) -> Self {
+        self.call_router = Some(call_router);
+        self
+    }
+
+    /// Span of a call in progress
+    pub fn call_span(&self, call_id: &str) -> Option<Span> {
+        self.calls
+            .lock()
+            .unwrap()
+            .get(call_id)
+            .map(|call| call.span.clone())
+    }
+
+    /// Number of calls with an open span
+    pub fn len(&self) -> usize {
+        self.calls.lock().unwrap().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Span of a received message, inside its call's span; an initial
+    /// INVITE opens the call's span. `trunk` is the trunk it came from.
+    pub fn transaction_span(&self, message: &SipMessage, trunk: Option<&str>) -> Span {
+        let Some(call_id) = message.call_id() else {
+            return info_span!("sip");
+        };
+        let name = match message {
+            SipMessage::Request(request) => match request.method() {
+                Some(method) => format!("SIP {}", method),
+                None => "SIP request".to_string(),
+            },
+            SipMessage::Response(response) => format!("SIP {}", response.status_code()),
+        };
+
+        let call = match (self.call_span(&call_id), message) {
+            (Some(call), _) => Some(call),
+            (None, SipMessage::Request(request))
+                if request.method() == Some(SipMethod::Invite) && request.to_tag().is_none() =>
+            {
+                let (caller, callee) = (from_uri(request), to_uri(request));
+                // Trunk calls belong to the tenant they are for
+                let tenant = match trunk {
+                    Some(_) => realm_of(&callee),
+                    None => realm_of(&caller),
+                };
+                Some(self.open(&call_id, tenant, trunk))
+            }
+            _ => None,
+        };
+        match call {
+            Some(call) => info_span!(parent: &call, "sip", otel.name = %name, call_id = %call_id),
+            None => info_span!("sip", otel.name = %name, call_id = %call_id),
+        }
+    }
+
+    /// A received message of `call_id` was handled: ends the span of an
+    /// initial INVITE that created no call
+    pub async fn transaction_done(&self, call_id: &str) {
+        let Some(call_router) = &self.call_router else {
+            return;
+        };
+        let pending = self
+            .calls
+            .lock()
+            .unwrap()
+            .get(call_id)
+            .is_some_and(|call| !call.established);
+        if !pending {
+            return;
+        }
+        if call_router.get_call_state(call_id).await.is_some() {
+            if let Some(call) = self.calls.lock().unwrap().get_mut(call_id) {
+                call.established = true;
+            }
+        } else {
+            self.end(call_id, "refused");
+        }
+    }
+
+    /// Record a call event as a span event; the call's span ends with it
+    pub fn record(&self, call_id: &str, event: &CallEvent) {
+        let span = match event {
+            CallEvent::Initiated(e) => {
+                // Calls started by the PBX have no INVITE of their own
+                let span = self.call_span(call_id).unwrap_or_else(|| {
+                    self.open(call_id, realm_of(&e.caller.uri().to_string()), None)
+                });
+                if let Some(call) = self.calls.lock().unwrap().get_mut(call_id) {
+                    call.established = true;
+                }
+                info!(parent: &span, "initiated");
+                return;
+            }
+            CallEvent::Ended(e) => {
+                let Some(call) = self.calls.lock().unwrap().remove(call_id) else {
+                    return;
+                };
+                info!(parent: &call.span, reason = ?e.reason, "terminated");
+                return;
+            }
+            _ => match self.call_span(call_id) {
+                Some(span) => span,
+                None => return,
+            },
+        };
+        match event {
+            CallEvent::Ringing(_) => info!(parent: &span, "ringing"),
+            CallEvent::EarlyMedia(_) => info!(parent: &span, "early media"),
+            CallEvent::Answered(_) => info!(parent: &span, "answered"),
+            CallEvent::Acknowledged(_) => info!(parent: &span, "acknowledged"),
+            CallEvent::Held(_) => info!(parent: &span, "held"),
+            CallEvent::Resumed(_) => info!(parent: &span, "resumed"),
+            CallEvent::Initiated(_) | CallEvent::Ended(_) => {}
+        }
+    }
+
+    /// Span of a hangup requested outside SIP (e.g. through the API): a
+    /// child of the current span, linked both ways with the call's span
+    pub fn hangup_span(&self, call_id: &str) -> Span {
+        let span = info_span!("call.hangup", call_id = %call_id);
+        if let Some(call) = self.call_span(call_id) {
+            span.add_link(call.context().span().span_context().clone());
+            call.add_link(span.context().span().span_context().clone());
+            info!(parent: &call, "hangup requested");
+        }
+        span
+    }
+
+    /// End a call's span without a call event
+    fn end(&self, call_id: &str, outcome: &'static str) {
+        if let Some(call) = self.calls.lock().unwrap().remove(call_id) {
+            info!(parent: &call.span, "{}", outcome);
+        }
+    }
+
+    fn open(&self, call_id: &str, tenant: Option<&str>, trunk: Option<&str>) -> Span {
+        let span = info_span!(
+            parent: None,
+            "call",
+            call_id = %call_id,
+            tenant = field::Empty,
+            trunk = field::Empty
+        );
+        if let Some(tenant) = tenant {
+            span.record("tenant", tenant);
+        }
+        if let Some(trunk) = trunk {
+            span.record("trunk", trunk);
+        }
+        self.calls.lock().unwrap().insert(
+            call_id.to_string(),
+            CallSpan {
+                span: span.clone(),
+                established: false,
+            },
+        );
+        span
+    }
+}
+
+impl Default for CallSpans {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+fn from_uri(request: &SipRequest) -> String {
+    request
+        .headers()
+        .iter()
+        .find_map(|h| match h {
+            Header::From(from) => Some(uri_from_header(from.value()).to_string()),
+            _ => None,
+        })
+        .unwrap_or_default()
+}
+
+fn to_uri(request: &SipRequest) -> String {
+    request
+        .headers()
+        .iter()
+        .find_map(|h| match h {
+            Header::To(to) => Some(uri_from_header(to.value()).to_string()),
+            _ => None,
+        })
+        .unwrap_or_default()
+}
+
+/// Record call events on the calls' spans
+pub fn spawn_call_span_recorder(bus: &dyn EventBus, spans: Arc<CallSpans>) -> JoinHandle<()> {
+    let mut rx = bus.subscribe();
+    tokio::spawn(async move {
+        loop {
+            match rx.recv().await {
+                Ok(envelope) => spans.record(&envelope.call_id, &envelope.event),
+                Err(broadcast::error::RecvError::Lagged(skipped)) => {
+                    warn!("Dropped {} call events (call spans lagging)", skipped);
+                }
+                Err(broadcast::error::RecvError::Closed) => break,
+            }
+        }
+    })
+}
+
+/// In-memory export of this crate's spans for the current thread
+#[cfg(test)]
+pub(crate) fn test_tracing() -> (
+    opentelemetry_sdk::testing::trace::InMemorySpanExporter,
+    TracerProvider,
+    tracing::subscriber::DefaultGuard,
+) {
+    use tracing_subscriber::prelude::*;
+
+    let exporter = opentelemetry_sdk::testing::trace::InMemorySpanExporter::default();
+    let provider = TracerProvider::builder()
+        .with_simple_exporter(exporter.clone())
+        .build();
+    let guard = tracing::subscriber::set_default(
+        tracing_subscriber::registry().with(layer(&provider, "yakyak-test")),
+    );
+    (exporter, provider, guard)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::application::call::CallApplicationService;
+    use crate::infrastructure::messaging::InProcessEventBus;
+    use crate::infrastructure::protocols::sip::Registrar;
+    use crate::test_support::{SipRequestBuilder, SipResponseBuilder};
+    use opentelemetry::Value;
+    use tracing::Instrument;
+
+    fn attribute(span: &SpanData, key: &str) -> Option<Value> {
+        span.attributes
+            .iter()
+            .find(|kv| kv.key.as_str() == key)
+            .map(|kv| kv.value.clone())
+    }
+
+    #[tokio::test]
+    async fn test_call_span_hierarchy() {
+        let (exporter, _provider, _guard) = test_tracing();
+        let bus = Arc::new(InProcessEventBus::default());
+        let router = Arc::new(
+            CallRouter::new(Arc::new(Registrar::new()))
+                .with_call_events(Arc::new(CallApplicationService::new(bus.clone()))),
+        );
+        let spans = Arc::new(CallSpans::new().with_call_router(router.clone()));
+        let recorder = spawn_call_span_recorder(bus.as_ref(), spans.clone());
+
+        // A call from a trunk to an acme user, held once, then hung up
+        let invite = SipRequestBuilder::invite("sip:+15550100@203.0.113.5", "sip:bob@acme.test")
+            .build();
+        let call_id = invite.call_id().unwrap();
+        let message = SipMessage::Request(invite.clone());
+        let transaction = spans.transaction_span(&message, Some("carrier"));
+        async {
+            router
+                .create_call(
+                    call_id.clone(),
+                    "sip:+15550100@203.0.113.5".to_string(),
+                    "sip:bob@acme.test".to_string(),
+                )
+                .await
+                .unwrap();
+            router.answer_call(&call_id).await.unwrap();
+        }
+        .instrument(transaction)
+        .await;
+        spans.transaction_done(&call_id).await;
+        assert_eq!(spans.len(), 1);
+
+        let ok = SipResponseBuilder::for_request(&invite, 200).to_tag("b1").build();
+        drop(spans.transaction_span(&SipMessage::Response(ok), None));
+        router.hold_call(&call_id).await.unwrap();
+        router.terminate_call(&call_id).await.unwrap();
+
+        // A refused INVITE ends its span right away
+        let refused = SipRequestBuilder::invite("sip:alice@acme.test", "sip:nobody@acme.test").build();
+        drop(spans.transaction_span(&SipMessage::Request(refused.clone()), None));
+        spans.transaction_done(&refused.call_id().unwrap()).await;
+
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert!(spans.is_empty());
+        recorder.abort();
+
+        let finished = exporter.get_finished_spans().unwrap();
+        let calls: Vec<&SpanData> = finished.iter().filter(|s| s.name == "call").collect();
+        assert_eq!(calls.len(), 2);
+        let call = calls
+            .iter()
+            .find(|s| attribute(s, "call_id") == Some(call_id.clone().into()))
+            .unwrap();
+        assert_eq!(attribute(call, "tenant"), Some("acme.test".into()));
+        assert_eq!(attribute(call, "trunk"), Some("carrier".into()));
+        assert_eq!(call.parent_span_id, opentelemetry::trace::SpanId::INVALID);
+        let events: Vec<&str> = call.events.events.iter().map(|e| e.name.as_ref()).collect();
+        assert_eq!(events, ["initiated", "ringing", "answered", "held", "terminated"]);
+
+        // Both transactions of the call sit in its span
+        let transactions: Vec<&SpanData> = finished
+            .iter()
+            .filter(|s| attribute(s, "call_id") == Some(call_id.clone().into()) && s.name != "call")
+            .collect();
+        let mut names: Vec<&str> = transactions.iter().map(|s| s.name.as_ref()).collect();
+        names.sort();
+        assert_eq!(names, ["SIP 200", "SIP INVITE"]);
+        for transaction in transactions {
+            assert_eq!(transaction.parent_span_id, call.span_context.span_id());
+            assert_eq!(transaction.span_context.trace_id(), call.span_context.trace_id());
+        }
+
+        let refused_call = calls.iter().find(|s| s.span_context != call.span_context).unwrap();
+        assert_eq!(attribute(refused_call, "tenant"), Some("acme.test".into()));
+        assert_eq!(attribute(refused_call, "trunk"), None);
+        assert_eq!(refused_call.events.events[0].name, "refused");
+    }
+
+    #[tokio::test]
+    async fn test_traceparent_round_trip() {
+        let (exporter, _provider, _guard) = test_tracing();
+        let remote = "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01";
+
+        let span = info_span!("request");
+        set_remote_parent(&span, remote, None);
+        let outbound = traceparent(&span).unwrap();
+        assert!(outbound.starts_with("00-4bf92f3577b34da6a3ce929d0e0e4736-"));
+        assert!(!outbound.contains("00f067aa0ba902b7"));
+        drop(span);
+
+        let finished = exporter.get_finished_spans().unwrap();
+        assert_eq!(
+            finished[0].parent_span_id,
+            opentelemetry::trace::SpanId::from_hex("00f067aa0ba902b7").unwrap()
+        );
+
+        // Not exported, nothing to continue
+        assert_eq!(traceparent(&Span::none()), None);
+        assert!(TelemetryConfig::default().validate().is_ok());
+        let config = TelemetryConfig {
+            sampling_ratio: 1.5,
+            ..Default::default()
+        };
+        assert!(config.validate().is_err());
+    }
+}
@@ -0,0 +1,442 @@
+//! Whisper-style HTTP transcription backend
+//!
+//! Posts the recording as `multipart/form-data` (`file`, `model`,
+//! `response_format=verbose_json` and `language` when known) and reads `text`
+//! from the JSON answer. The confidence is the service's `confidence` when it
+//! reports one, else derived from the segments' `avg_logprob`.
+//!
+//! Plain HTTP only: the service is expected on the local network or behind
+//! a TLS-terminating proxy.
+
+use super::{Transcript, TranscriptionProvider};
+use crate::domain::shared::SecretBox;
+use crate::infrastructure::telemetry;
+use async_trait::async_trait;
+use std::path::Path;
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+use tracing::{debug, Span};
+use uuid::Uuid;
+
+/// Larger answers are refused (a transcript of a few minutes is a few KB)
+const MAX_RESPONSE_BYTES: usize = 1024 * 1024;
+
+/// Posts recordings to a self-hosted speech-to-text service
+pub struct HttpTranscriptionProvider {
+    /// `host:port` connected to and sent as Host
+    authority: String,
+    path: String,
+    api_key: Option<SecretBox>,
+    model: String,
+    timeout: Duration,
+    max_upload_bytes: u64,
+}
+
+impl HttpTranscriptionProvider {
+    pub fn new(
+        endpoint: &str,
+        api_key: Option<SecretBox>,
+        model: String,
+        timeout: Duration,
+        max_upload_bytes: u64,
+    ) -> Result<Self, String> {
+        let rest = endpoint.strip_prefix("http://").ok_or_else(|| {
+            format!(
+                "Unsupported transcription endpoint {} (only http:// is supported)",
+                endpoint
+            )
+        })?;
+        let (authority, path) = match rest.find('/') {
+            Some(i) => (&rest[..i], &rest[i..]),
+            None => (rest, "/"),
+        };
+        if authority.is_empty() {
+            return Err(format!("Transcription endpoint {} has no host", endpoint));
+        }
+        let has_port = match authority.rfind(']') {
+            Some(i) => authority[i..].contains(':'),
+            None => authority.contains(':'),
+        };
+        let authority = if has_port {
+            authority.to_string()
+        } else {
+            format!("{}:80", authority)
+        };
+
+        Ok(Self {
+            authority,
+            path: path.to_string(),
+            api_key,
+            model,
+            timeout,
+            max_upload_bytes,
+        })
+    }
+
+    async fn post(&self, boundary: &str, body: Vec<u8>) -> Result<Vec<u8>, String> {
+        let mut head = format!(
+            "POST {} HTTP/1.1\r\n\
+             Host: {}\r\n\
+             User-Agent: yakyak\r\n\
+             Accept: application/json\r\n\
+             Content-Type: multipart/form-data; boundary={}\r\n\
+             Content-Length: {}\r\n",
+            self.path,
+            self.authority,
+            boundary,
+            body.len()
+        );
+        if let Some(api_key) = &self.api_key {
+            head.push_str(&format!("Authorization: Bearer {}\r\n", api_key.expose()));
+        }
+        // Continue the current trace in the service
+        if let Some(traceparent) = telemetry::traceparent(&Span::current()) {
+            head.push_str(&format!("traceparent: {}\r\n", traceparent));
+        }
+        head.push_str("Connection: close\r\n\r\n");
+
+        let mut stream = TcpStream::connect(&self.authority)
+            .await
+            .map_err(|e| format!("Failed to connect to {}: {}", self.authority, e))?;
+        let sent = async {
+            stream.write_all(head.as_bytes()).await?;
+            stream.write_all(&body).await
+        }
+        .await;
+        sent.map_err(|e| format!("Failed to send recording: {}", e))?;
+
+        let mut response = Vec::new();
+        (&mut stream)
+            .take(MAX_RESPONSE_BYTES as u64 + 1)
+            .read_to_end(&mut response)
+            .await
+            .map_err(|e| format!("Failed to read transcription: {}", e))?;
+        if response.len() > MAX_RESPONSE_BYTES {
+            return Err(format!(
+                "Transcription response exceeds {} bytes",
+                MAX_RESPONSE_BYTES
+            ));
+        }
+        response_body(&response)
+    }
+}
+
+#[async_trait]
+impl TranscriptionProvider for HttpTranscriptionProvider {
+    async fn transcribe(&self, wav: &Path, language: Option<&str>) -> Result<Transcript, String> {
+        let size = tokio::fs::metadata(wav)
+            .await
+            .map_err(|e| format!("Failed to read {}: {}", wav.display(), e))?
+            .len();
+        if size > self.max_upload_bytes {
+            return Err(format!(
+                "Recording is {} bytes, over the {} byte upload limit",
+                size, self.max_upload_bytes
+            ));
+        }
+        let audio = tokio::fs::read(wav)
+            .await
+            .map_err(|e| format!("Failed to read {}: {}", wav.display(), e))?;
+
+        // Whisper takes ISO 639-1 codes: "de-DE" -> "de"
+        let language = language
+            .and_then(|l| l.split(['-', '_']).next())
+            .map(str::to_lowercase)
+            .filter(|l| !l.is_empty());
+        let mut fields = vec![
+            ("model", self.model.clone()),
+            ("response_format", "verbose_json".to_string()),
+        ];
+        if let Some(language) = &language {
+            fields.push(("language", language.clone()));
+        }
+        let file_name = wav
+            .file_name()
+            .map(|name| name.to_string_lossy().into_owned())
+            .unwrap_or_else(|| "voicemail.wav".to_string());
+        let boundary = format!("yakyak-{}", Uuid::new_v4().simple());
+        let body = multipart_body(&boundary, &fields, &file_name, &audio);
+
+        debug!(
+            "Transcribing {} ({} bytes, language {:?})",
+            wav.display(),
+            size,
+            language
+        );
+        let response = tokio::time::timeout(self.timeout, self.post(&boundary, body))
+            .await
+            .map_err(|_| format!("Transcription timed out after {:?}", self.timeout))??;
+        parse_transcript(&response, language)
+    }
+}
+
+/// `multipart/form-data` body with text `fields` and the recording as `file`
+fn multipart_body(
+    boundary: &str,
+    fields: &[(&str, String)],
+    file_name: &str,
+    audio: &[u8],
+) -> Vec<u8> {
+    let mut body = Vec::with_capacity(audio.len() + 512);
+    for (name, value) in fields {
+        body.extend_from_slice(
+            format!(
+                "--{}\r\nContent-Disposition: form-data; name=\"{}\"\r\n\r\n{}\r\n",
+                boundary, name, value
+            )
+            .as_bytes(),
+        );
+    }
+    body.extend_from_slice(
+        format!(
+            "--{}\r\nContent-Disposition: form-data; name=\"file\"; filename=\"{}\"\r\n\
+             Content-Type: audio/wav\r\n\r\n",
+            boundary,
+            file_name.replace('"', "")
+        )
+        .as_bytes(),
+    );
+    body.extend_from_slice(audio);
+    body.extend_from_slice(format!("\r\n--{}--\r\n", boundary).as_bytes());
+    body
+}
+
+/// Body of a successful HTTP response (chunked encoding removed)
+fn response_body(response: &[u8]) -> Result<Vec<u8>, String> {
+    let split = response
+        .windows(4)
+        .position(|w| w == b"\r\n\r\n")
+        .ok_or("Malformed transcription response")?;
+    let head = String::from_utf8_lossy(&response[..split]);
+    let body = &response[split + 4..];
+
+    let mut lines = head.lines();
+    let status: u16 = lines
+        .next()
+        .and_then(|line| line.split_whitespace().nth(1))
+        .and_then(|code| code.parse().ok())
+        .ok_or("Malformed transcription response status")?;
+    let chunked = lines.any(|line| {
+        line.split_once(':').is_some_and(|(name, value)| {
+            name.trim().eq_ignore_ascii_case("transfer-encoding")
+                && value.to_ascii_lowercase().contains("chunked")
+        })
+    });
+    let body = if chunked {
+        dechunk(body)?
+    } else {
+        body.to_vec()
+    };
+
+    if !(200..300).contains(&status) {
+        let detail: String = String::from_utf8_lossy(&body).chars().take(200).collect();
+        return Err(format!(
+            "Transcription service answered {}: {}",
+            status,
+            detail.trim()
+        ));
+    }
+    Ok(body)
+}
+
+fn dechunk(mut body: &[u8]) -> Result<Vec<u8>, String> {
+    let mut out = Vec::new();
+    loop {
+        let line_end = body
+            .windows(2)
+            .position(|w| w == b"\r\n")
+            .ok_or("Truncated chunked response")?;
+        let size_text = String::from_utf8_lossy(&body[..line_end]);
+        let size = usize::from_str_radix(size_text.split(';').next().unwrap_or("").trim(), 16)
+            .map_err(|_| format!("Invalid chunk size {:?}", size_text))?;
+        body = &body[line_end + 2..];
+        if size == 0 {
+            return Ok(out);
+        }
+        if body.len() < size {
+            return Err("Truncated chunked response".to_string());
+        }
+        out.extend_from_slice(&body[..size]);
+        body = body.get(size + 2..).unwrap_or_default();
+    }
+}
+
+/// Transcript of a Whisper-style JSON answer
+fn parse_transcript(body: &[u8], language: Option<String>) -> Result<Transcript, String> {
+    let json: serde_json::Value = serde_json::from_slice(body)
+        .map_err(|e| format!("Invalid transcription response: {}", e))?;
+    let text = json
+        .get("text")
+        .and_then(|text| text.as_str())
+        .ok_or("Transcription response has no text")?
+        .trim()
+        .to_string();
+
+    let confidence = json
+        .get("confidence")
+        .and_then(|c| c.as_f64())
+        .or_else(|| {
+            let logprobs: Vec<f64> = json
+                .get("segments")?
+                .as_array()?
+                .iter()
+                .filter_map(|segment| segment.get("avg_logprob")?.as_f64())
+                .collect();
+            (!logprobs.is_empty())
+                .then(|| (logprobs.iter().sum::<f64>() / logprobs.len() as f64).exp())
+        })
+        .map(|c| c.clamp(0.0, 1.0) as f32);
+
+    Ok(Transcript {
+        text,
+        confidence,
+        language: json
+            .get("language")
+            .and_then(|l| l.as_str())
+            .map(str::to_string)
+            .or(language),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::net::TcpListener;
+
+    /// Read one HTTP request (head and Content-Length body)
+    async fn read_request(stream: &mut TcpStream) -> (String, Vec<u8>) {
+        let mut data = Vec::new();
+        let mut buf = [0u8; 4096];
+        loop {
+            let n = stream.read(&mut buf).await.unwrap();
+            assert!(n > 0, "connection closed mid-request");
+            data.extend_from_slice(&buf[..n]);
+            if let Some(split) = data.windows(4).position(|w| w == b"\r\n\r\n") {
+                let head = String::from_utf8_lossy(&data[..split]).to_string();
+                let length: usize = head
+                    .lines()
+                    .find_map(|l| l.strip_prefix("Content-Length: "))
+                    .unwrap()
+                    .parse()
+                    .unwrap();
+                if data.len() >= split + 4 + length {
+                    return (head, data[split + 4..split + 4 + length].to_vec());
+                }
+            }
+        }
+    }
+
+    fn recording(name: &str, bytes: &[u8]) -> std::path::PathBuf {
+        let dir = std::env::temp_dir().join(format!("yakyak-transcription-{}", Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join(name);
+        std::fs::write(&path, bytes).unwrap();
+        path
+    }
+
+    #[tokio::test]
+    async fn test_posts_multipart_recording() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let server = tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            let request = read_request(&mut stream).await;
+            let json = r#"{"text":" Call me back about the invoice. ","language":"german","segments":[{"avg_logprob":-0.1},{"avg_logprob":-0.3}]}"#;
+            let response = format!(
+                "HTTP/1.1 200 OK\r\nContent-Type: application/json\r\nTransfer-Encoding: chunked\r\n\r\n{:x}\r\n{}\r\n0\r\n\r\n",
+                json.len(),
+                json
+            );
+            stream.write_all(response.as_bytes()).await.unwrap();
+            request
+        });
+
+        let wav = recording("msg001.wav", b"RIFF....WAVEfmt fake-audio");
+        let provider = HttpTranscriptionProvider::new(
+            &format!("http://{}/v1/audio/transcriptions", addr),
+            Some(SecretBox::new("secret-key")),
+            "whisper-1".to_string(),
+            Duration::from_secs(5),
+            1024,
+        )
+        .unwrap();
+        let transcript = provider.transcribe(&wav, Some("de-DE")).await.unwrap();
+
+        assert_eq!(transcript.text, "Call me back about the invoice.");
+        assert!((transcript.confidence.unwrap() - (-0.2f64).exp() as f32).abs() < 1e-4);
+        assert_eq!(transcript.language.as_deref(), Some("german"));
+
+        let (head, body) = server.await.unwrap();
+        let body = String::from_utf8_lossy(&body).to_string();
+        assert!(head.starts_with("POST /v1/audio/transcriptions HTTP/1.1\r\n"));
+        assert!(head.contains(&format!("Host: {}", addr)));
+        assert!(head.contains("Authorization: Bearer secret-key"));
+        let boundary = head
+            .lines()
+            .find_map(|l| l.strip_prefix("Content-Type: multipart/form-data; boundary="))
+            .unwrap();
+        assert!(body.contains(&format!(
+            "--{}\r\nContent-Disposition: form-data; name=\"file\"; filename=\"msg001.wav\"\r\n\
+             Content-Type: audio/wav\r\n\r\nRIFF....WAVEfmt fake-audio\r\n",
+            boundary
+        )));
+        assert!(body.contains("name=\"model\"\r\n\r\nwhisper-1\r\n"));
+        assert!(body.contains("name=\"response_format\"\r\n\r\nverbose_json\r\n"));
+        assert!(body.contains("name=\"language\"\r\n\r\nde\r\n"));
+        assert!(body.ends_with(&format!("--{}--\r\n", boundary)));
+    }
+
+    #[tokio::test]
+    async fn test_upload_limit_timeout_and_errors() {
+        let wav = recording("big.wav", &[0u8; 2048]);
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let endpoint = format!("http://{}/asr", addr);
+        let provider = |max_upload_bytes| {
+            HttpTranscriptionProvider::new(
+                &endpoint,
+                None,
+                "base".to_string(),
+                Duration::from_millis(300),
+                max_upload_bytes,
+            )
+            .unwrap()
+        };
+
+        let err = provider(1024).transcribe(&wav, None).await.unwrap_err();
+        assert!(err.contains("upload limit"), "{}", err);
+
+        // Accepts the upload but never answers
+        let silent = tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            let request = read_request(&mut stream).await;
+            tokio::time::sleep(Duration::from_secs(1)).await;
+            (listener, request)
+        });
+        let err = provider(4096).transcribe(&wav, None).await.unwrap_err();
+        assert!(err.contains("timed out"), "{}", err);
+        let (listener, (_, body)) = silent.await.unwrap();
+        assert!(!String::from_utf8_lossy(&body).contains("name=\"language\""));
+
+        tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            read_request(&mut stream).await;
+            stream
+                .write_all(b"HTTP/1.1 503 Service Unavailable\r\nContent-Length: 4\r\n\r\nbusy")
+                .await
+                .unwrap();
+        });
+        let err = provider(4096).transcribe(&wav, None).await.unwrap_err();
+        assert_eq!(err, "Transcription service answered 503: busy");
+
+        assert!(HttpTranscriptionProvider::new(
+            "https://asr.example.com/v1",
+            None,
+            "base".to_string(),
+            Duration::from_secs(1),
+            1
+        )
+        .is_err());
+    }
+}
@@ -0,0 +1,429 @@
+//! Live captions of bridged calls
+//!
+//! A session taps the decoded audio of one or both legs of a call's media
+//! bridge and streams each leg to the provider's streaming variant. Every
+//! segment the provider yields, interim ones included, is published as a
+//! [`CaptionEvent`] for the events WebSocket and the browser's data channel.
+//! Taps drop their oldest audio rather than wait for a slow provider, so
+//! captions never hold up the call. A session ends with its call or when
+//! stopped; the call's CDR records that it was captioned.
+
+use super::{TranscriptSegment, TranscriptionProvider};
+use crate::infrastructure::media::{media_tap, BridgeLeg, MediaTap, DEFAULT_TAP_CHUNKS};
+use crate::infrastructure::protocols::sip::{CallRouter, CallState};
+use chrono::{DateTime, Utc};
+use futures::stream::{BoxStream, StreamExt};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use tokio::sync::broadcast;
+use tokio::task::JoinHandle;
+use tracing::{debug, info};
+
+/// Call leg a caption is of
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CaptionLeg {
+    Caller,
+    Callee,
+}
+
+impl CaptionLeg {
+    fn bridge_leg(self) -> BridgeLeg {
+        match self {
+            CaptionLeg::Caller => BridgeLeg::A,
+            CaptionLeg::Callee => BridgeLeg::B,
+        }
+    }
+}
+
+/// Legs a session captions
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CaptionLegs {
+    Caller,
+    Callee,
+    #[default]
+    Both,
+}
+
+impl CaptionLegs {
+    fn legs(self) -> &'static [CaptionLeg] {
+        match self {
+            CaptionLegs::Caller => &[CaptionLeg::Caller],
+            CaptionLegs::Callee => &[CaptionLeg::Callee],
+            CaptionLegs::Both => &[CaptionLeg::Caller, CaptionLeg::Callee],
+        }
+    }
+}
+
+/// Caption of a live call
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct CaptionEvent {
+    pub call_id: String,
+    pub leg: CaptionLeg,
+    pub text: String,
+    /// Interim captions are revised by later ones until a final one
+    pub is_final: bool,
+    /// Offsets into the leg's audio since captioning started
+    pub start_ms: u64,
+    pub end_ms: u64,
+    pub at: DateTime<Utc>,
+}
+
+/// Live transcription errors
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+pub enum LiveTranscriptionError {
+    #[error("Call {0} not found")]
+    CallNotFound(String),
+    #[error("Call {0} has no bridged media")]
+    NoMedia(String),
+    #[error("Call {0} is already captioned")]
+    AlreadyRunning(String),
+    #[error("Call {0} is not captioned")]
+    NotRunning(String),
+    #[error("Transcription provider refused: {0}")]
+    Provider(String),
+}
+
+/// A running session
+#[derive(Debug, Clone, Serialize)]
+pub struct LiveTranscriptionInfo {
+    pub call_id: String,
+    pub language: Option<String>,
+    pub legs: CaptionLegs,
+    pub started_at: DateTime<Utc>,
+}
+
+struct Session {
+    info: LiveTranscriptionInfo,
+    taps: Vec<MediaTap>,
+    /// Ends the session with the call
+    watcher: Option<JoinHandle<()>>,
+}
+
+/// Live captions of calls
+pub struct LiveTranscriptionService {
+    provider: Arc<dyn TranscriptionProvider>,
+    router: Arc<CallRouter>,
+    sessions: Mutex<HashMap<String, Session>>,
+    events: broadcast::Sender<CaptionEvent>,
+    /// PCM chunks a tap holds before dropping the oldest
+    tap_chunks: usize,
+}
+
+impl LiveTranscriptionService {
+    pub fn new(provider: Arc<dyn TranscriptionProvider>, router: Arc<CallRouter>) -> Self {
+        let (events, _) = broadcast::channel(1000);
+        Self {
+            provider,
+            router,
+            sessions: Mutex::new(HashMap::new()),
+            events,
+            tap_chunks: DEFAULT_TAP_CHUNKS,
+        }
+    }
+
+    /// Audio chunks (20 ms each, typically) buffered per leg
+    pub fn with_tap_chunks(mut self, chunks: usize) -> Self {
+        self.tap_chunks = chunks;
+        self
+    }
+
+    /// Captions of every session
+    pub fn subscribe(&self) -> broadcast::Receiver<CaptionEvent> {
+        self.events.subscribe()
+    }
+
+    pub fn session(&self, call_id: &str) -> Option<LiveTranscriptionInfo> {
+        let sessions = self.sessions.lock().unwrap();
+        sessions.get(call_id).map(|session| session.info.clone())
+    }
+
+    pub fn sessions(&self) -> Vec<LiveTranscriptionInfo> {
+        let sessions = self.sessions.lock().unwrap();
+        sessions.values().map(|session| session.info.clone()).collect()
+    }
+
+    /// Caption `legs` of a bridged call; `language` is a hint for the
+    /// provider
+    pub async fn start(
+        self: &Arc<Self>,
+        call_id: &str,
+        language: Option<String>,
+        legs: CaptionLegs,
+    ) -> Result<LiveTranscriptionInfo, LiveTranscriptionError> {
+        if self.sessions.lock().unwrap().contains_key(call_id) {
+            return Err(LiveTranscriptionError::AlreadyRunning(call_id.to_string()));
+        }
+        let mut state = self
+            .router
+            .watch_call_state(call_id)
+            .await
+            .ok_or_else(|| LiveTranscriptionError::CallNotFound(call_id.to_string()))?;
+        let bridge = self
+            .router
+            .media_bridge(call_id)
+            .await
+            .ok_or_else(|| LiveTranscriptionError::NoMedia(call_id.to_string()))?;
+
+        let mut taps: Vec<MediaTap> = Vec::new();
+        for &leg in legs.legs() {
+            let (tap, audio) = media_tap(leg.bridge_leg(), self.tap_chunks);
+            let segments = match self
+                .provider
+                .transcribe_stream(audio.into_stream(), language.as_deref())
+                .await
+            {
+                Ok(segments) => segments,
+                Err(e) => {
+                    taps.iter().for_each(MediaTap::close);
+                    return Err(LiveTranscriptionError::Provider(e));
+                }
+            };
+            bridge.add_tap(tap.clone());
+            taps.push(tap);
+            tokio::spawn(publish_captions(
+                self.events.clone(),
+                call_id.to_string(),
+                leg,
+                segments,
+            ));
+        }
+
+        let info = LiveTranscriptionInfo {
+            call_id: call_id.to_string(),
+            language,
+            legs,
+            started_at: Utc::now(),
+        };
+        {
+            let mut sessions = self.sessions.lock().unwrap();
+            if sessions.contains_key(call_id) {
+                taps.iter().for_each(MediaTap::close);
+                return Err(LiveTranscriptionError::AlreadyRunning(call_id.to_string()));
+            }
+            sessions.insert(
+                call_id.to_string(),
+                Session {
+                    info: info.clone(),
+                    taps,
+                    watcher: None,
+                },
+            );
+        }
+
+        let service = self.clone();
+        let id = call_id.to_string();
+        let watcher = tokio::spawn(async move {
+            loop {
+                if matches!(*state.borrow_and_update(), CallState::Terminated) {
+                    break;
+                }
+                // Closed once the call is gone
+                if state.changed().await.is_err() {
+                    break;
+                }
+            }
+            if service.close(&id).is_some() {
+                info!("Live captions of call {} ended with the call", id);
+            }
+        });
+        match self.sessions.lock().unwrap().get_mut(call_id) {
+            Some(session) => session.watcher = Some(watcher),
+            None => watcher.abort(),
+        }
+
+        self.router.mark_live_transcribed(call_id).await;
+        info!(
+            "Live captions of call {} started ({:?}, language {:?})",
+            call_id, info.legs, info.language
+        );
+        Ok(info)
+    }
+
+    /// Stop captioning a call; captions of audio already sent may follow
+    pub fn stop(&self, call_id: &str) -> Result<LiveTranscriptionInfo, LiveTranscriptionError> {
+        let session = self
+            .close(call_id)
+            .ok_or_else(|| LiveTranscriptionError::NotRunning(call_id.to_string()))?;
+        if let Some(watcher) = session.watcher {
+            watcher.abort();
+        }
+        info!("Live captions of call {} stopped", call_id);
+        Ok(session.info)
+    }
+
+    /// Remove a session and end its audio
+    fn close(&self, call_id: &str) -> Option<Session> {
+        let session = self.sessions.lock().unwrap().remove(call_id)?;
+        session.taps.iter().for_each(MediaTap::close);
+        Some(session)
+    }
+}
+
+/// Publish the segments of one leg until the provider ends them
+async fn publish_captions(
+    events: broadcast::Sender<CaptionEvent>,
+    call_id: String,
+    leg: CaptionLeg,
+    mut segments: BoxStream<'static, TranscriptSegment>,
+) {
+    while let Some(segment) = segments.next().await {
+        // Nobody listening is not an error
+        let _ = events.send(CaptionEvent {
+            call_id: call_id.clone(),
+            leg,
+            text: segment.text,
+            is_final: segment.is_final,
+            start_ms: segment.start_ms,
+            end_ms: segment.end_ms,
+            at: Utc::now(),
+        });
+    }
+    debug!("Captions of call {} ({:?}) ended", call_id, leg);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::{PcmStream, SegmentStream, Transcript};
+    use super::*;
+    use crate::domain::cdr::CdrRepository;
+    use crate::infrastructure::media::{
+        BridgeDirection, G711Type, MediaBridge, MediaStream, RtpPacket,
+    };
+    use crate::infrastructure::persistence::MemoryCdrRepository;
+    use crate::infrastructure::protocols::sip::Registrar;
+    use async_trait::async_trait;
+    use futures::stream;
+    use std::path::Path;
+    use std::time::Duration;
+
+    /// Says "hello" for loud audio and "goodbye" for negative samples: an
+    /// interim segment per chunk, a final one every second chunk
+    struct ScriptedProvider;
+
+    #[async_trait]
+    impl TranscriptionProvider for ScriptedProvider {
+        async fn transcribe(&self, _wav: &Path, _language: Option<&str>) -> Result<Transcript, String> {
+            Err("not used".to_string())
+        }
+
+        async fn transcribe_stream(
+            &self,
+            audio: PcmStream,
+            _language: Option<&str>,
+        ) -> Result<SegmentStream, String> {
+            Ok(audio
+                .enumerate()
+                .flat_map(|(i, chunk)| {
+                    let word = if chunk[0] > 0 { "hello" } else { "goodbye" };
+                    let (start_ms, end_ms) = ((i as u64 / 2) * 40, (i as u64 + 1) * 20);
+                    let mut segments = vec![TranscriptSegment {
+                        text: word.to_string(),
+                        is_final: false,
+                        start_ms,
+                        end_ms,
+                    }];
+                    if i % 2 == 1 {
+                        segments.push(TranscriptSegment {
+                            text: format!("{} {}", word, word),
+                            is_final: true,
+                            start_ms,
+                            end_ms,
+                        });
+                    }
+                    stream::iter(segments)
+                })
+                .boxed())
+        }
+    }
+
+    fn packet(sequence: u16, sample: i16) -> RtpPacket {
+        RtpPacket::new(
+            0,
+            sequence,
+            sequence as u32 * 160,
+            0x5678,
+            G711Type::PCMU.encode(&[sample; 160]),
+        )
+    }
+
+    #[tokio::test]
+    async fn test_captions_follow_legs_and_end_with_call() {
+        let cdrs = Arc::new(MemoryCdrRepository::new());
+        let router = Arc::new(
+            CallRouter::new(Arc::new(Registrar::new())).with_cdr_repository(cdrs.clone()),
+        );
+        router
+            .create_call(
+                "call-1".to_string(),
+                "sip:alice@example.com".to_string(),
+                "sip:bob@example.com".to_string(),
+            )
+            .await
+            .unwrap();
+        let caller = Arc::new(MediaStream::new(10170, 0, 8000).await.unwrap());
+        let callee = Arc::new(MediaStream::new(10180, 0, 8000).await.unwrap());
+        let bridge = Arc::new(MediaBridge::new(caller, callee));
+        router.set_media_bridge("call-1", bridge.clone()).await;
+
+        let service = Arc::new(LiveTranscriptionService::new(
+            Arc::new(ScriptedProvider),
+            router.clone(),
+        ));
+        let mut captions = service.subscribe();
+        assert_eq!(
+            service.start("call-2", None, CaptionLegs::Both).await.unwrap_err(),
+            LiveTranscriptionError::CallNotFound("call-2".to_string())
+        );
+        service
+            .start("call-1", Some("en".to_string()), CaptionLegs::Both)
+            .await
+            .unwrap();
+        assert_eq!(
+            service.start("call-1", None, CaptionLegs::Caller).await.unwrap_err(),
+            LiveTranscriptionError::AlreadyRunning("call-1".to_string())
+        );
+        assert!(cdrs.get_by_call_id("call-1").await.unwrap().unwrap().live_transcribed);
+
+        for sequence in 0..2 {
+            bridge.adapt(BridgeDirection::AToB, packet(sequence, 1000));
+            bridge.adapt(BridgeDirection::BToA, packet(sequence, -1000));
+        }
+        let mut events = Vec::new();
+        while events.len() < 6 {
+            let event = tokio::time::timeout(Duration::from_secs(2), captions.recv())
+                .await
+                .unwrap()
+                .unwrap();
+            events.push(event);
+        }
+        for (leg, word) in [(CaptionLeg::Caller, "hello"), (CaptionLeg::Callee, "goodbye")] {
+            let of_leg: Vec<&CaptionEvent> = events.iter().filter(|e| e.leg == leg).collect();
+            assert!(of_leg.iter().all(|e| e.call_id == "call-1" && e.text.starts_with(word)));
+            let finals: Vec<bool> = of_leg.iter().map(|e| e.is_final).collect();
+            assert_eq!(finals, vec![false, false, true]);
+            assert_eq!(of_leg[2].text, format!("{} {}", word, word));
+            assert!(of_leg.windows(2).all(|w| w[0].start_ms <= w[1].start_ms));
+        }
+
+        router.discard_call("call-1").await;
+        for _ in 0..100 {
+            if service.session("call-1").is_none() {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        assert!(service.session("call-1").is_none());
+        assert_eq!(
+            service.stop("call-1").unwrap_err(),
+            LiveTranscriptionError::NotRunning("call-1".to_string())
+        );
+
+        // Audio after the end is not captioned
+        bridge.adapt(BridgeDirection::AToB, packet(2, 1000));
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert!(captions.try_recv().is_err());
+    }
+}
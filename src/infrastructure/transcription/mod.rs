@@ -0,0 +1,227 @@
+//! Voicemail transcription and live captions
+//!
+//! Recordings are turned into text by a [`TranscriptionProvider`]: either
+//! disabled (the default) or a self-hosted Whisper-style HTTP service. After
+//! a deposit, [`VoicemailTranscriber`] transcribes the message in the
+//! background, stores the text with it and sends the email notification.
+//! A failing provider only costs the transcript; the message is delivered
+//! and announced regardless.
+//!
+//! Providers with a streaming API also caption calls as they happen: see
+//! [`LiveTranscriptionService`].
+
+mod http;
+mod live;
+mod voicemail;
+
+pub use http::HttpTranscriptionProvider;
+pub use live::{
+    CaptionEvent, CaptionLeg, CaptionLegs, LiveTranscriptionError, LiveTranscriptionInfo,
+    LiveTranscriptionService,
+};
+pub use voicemail::{TranscribingVoicemailRepository, VoicemailEmailSender, VoicemailTranscriber};
+
+use crate::domain::shared::SecretBox;
+use async_trait::async_trait;
+use futures::stream::BoxStream;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::path::Path;
+use std::sync::Arc;
+
+/// Text of a recording
+#[derive(Debug, Clone, PartialEq)]
+pub struct Transcript {
+    pub text: String,
+    /// 0.0 - 1.0, when the backend reports one
+    pub confidence: Option<f32>,
+    /// Language the backend detected or was told
+    pub language: Option<String>,
+}
+
+/// Piece of a live transcript
+#[derive(Debug, Clone, PartialEq)]
+pub struct TranscriptSegment {
+    pub text: String,
+    /// Interim segments are revised by later ones until a final one
+    pub is_final: bool,
+    /// Offsets into the audio streamed so far
+    pub start_ms: u64,
+    pub end_ms: u64,
+}
+
+/// Live audio: chunks of 8 kHz mono PCM
+pub type PcmStream = BoxStream<'static, Vec<i16>>;
+
+/// Segments of a live transcript, in order; ends after the audio does
+pub type SegmentStream = BoxStream<'static, TranscriptSegment>;
+
+/// Speech-to-text backend
+#[async_trait]
+pub trait TranscriptionProvider: Send + Sync {
+    /// Transcribe a WAV file; `language` is a hint (BCP 47 or ISO 639-1)
+    async fn transcribe(&self, wav: &Path, language: Option<&str>) -> Result<Transcript, String>;
+
+    /// Transcribe audio as it arrives, yielding interim and final segments
+    ///
+    /// Backends without a streaming API refuse.
+    async fn transcribe_stream(
+        &self,
+        audio: PcmStream,
+        language: Option<&str>,
+    ) -> Result<SegmentStream, String> {
+        let _ = (audio, language);
+        Err("Streaming transcription is not supported".to_string())
+    }
+}
+
+/// Provider used when transcription is not configured
+pub struct DisabledTranscription;
+
+#[async_trait]
+impl TranscriptionProvider for DisabledTranscription {
+    async fn transcribe(&self, _wav: &Path, _language: Option<&str>) -> Result<Transcript, String> {
+        Err("Transcription is disabled".to_string())
+    }
+}
+
+/// Transcription settings
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct TranscriptionConfig {
+    pub enabled: bool,
+    /// Whisper-style endpoint receiving the multipart upload
+    /// (`http://host:port/v1/audio/transcriptions`)
+    pub endpoint: Option<String>,
+    /// Sent as Bearer token when set
+    pub api_key: Option<SecretBox>,
+    pub model: String,
+    /// Seconds allowed for the whole request, upload included
+    pub timeout_secs: u64,
+    /// Larger recordings are not uploaded
+    pub max_upload_bytes: u64,
+    /// Language hint for users without a locale
+    pub default_language: Option<String>,
+    /// Per-tenant settings, keyed by realm
+    pub tenants: HashMap<String, TenantTranscription>,
+}
+
+impl Default for TranscriptionConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            endpoint: None,
+            api_key: None,
+            model: "whisper-1".to_string(),
+            timeout_secs: 60,
+            max_upload_bytes: 25 * 1024 * 1024,
+            default_language: None,
+            tenants: HashMap::new(),
+        }
+    }
+}
+
+/// Transcription settings of one tenant
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct TenantTranscription {
+    pub enabled: bool,
+    /// Language hint for the tenant's users without a locale
+    pub language: Option<String>,
+}
+
+impl Default for TenantTranscription {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            language: None,
+        }
+    }
+}
+
+impl TranscriptionConfig {
+    /// Whether messages of users in `realm` are transcribed
+    pub fn enabled_for(&self, realm: Option<&str>) -> bool {
+        self.enabled
+            && realm
+                .and_then(|realm| self.tenants.get(realm))
+                .is_none_or(|tenant| tenant.enabled)
+    }
+
+    /// Language hint: the user's locale, else the tenant's, else the default
+    pub fn language_for(&self, realm: Option<&str>, locale: Option<&str>) -> Option<String> {
+        locale
+            .filter(|locale| !locale.trim().is_empty())
+            .map(str::to_string)
+            .or_else(|| {
+                realm
+                    .and_then(|realm| self.tenants.get(realm))
+                    .and_then(|tenant| tenant.language.clone())
+            })
+            .or_else(|| self.default_language.clone())
+    }
+
+    /// Provider for these settings; disabled when transcription is off or
+    /// the endpoint is unusable
+    pub fn provider(&self) -> Result<Arc<dyn TranscriptionProvider>, String> {
+        match (&self.endpoint, self.enabled) {
+            (Some(endpoint), true) => Ok(Arc::new(HttpTranscriptionProvider::new(
+                endpoint,
+                self.api_key.clone(),
+                self.model.clone(),
+                std::time::Duration::from_secs(self.timeout_secs),
+                self.max_upload_bytes,
+            )?)),
+            (None, true) => Err("Transcription is enabled but no endpoint is set".to_string()),
+            (_, false) => Ok(Arc::new(DisabledTranscription)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_tenant_settings() {
+        let mut config = TranscriptionConfig {
+            enabled: true,
+            default_language: Some("en".to_string()),
+            ..Default::default()
+        };
+        config.tenants.insert(
+            "quiet.example.com".to_string(),
+            TenantTranscription {
+                enabled: false,
+                language: None,
+            },
+        );
+        config.tenants.insert(
+            "de.example.com".to_string(),
+            TenantTranscription {
+                enabled: true,
+                language: Some("de".to_string()),
+            },
+        );
+
+        assert!(config.enabled_for(Some("example.com")));
+        assert!(config.enabled_for(None));
+        assert!(!config.enabled_for(Some("quiet.example.com")));
+
+        assert_eq!(
+            config.language_for(Some("de.example.com"), Some("fr-CA")),
+            Some("fr-CA".to_string())
+        );
+        assert_eq!(
+            config.language_for(Some("de.example.com"), None),
+            Some("de".to_string())
+        );
+        assert_eq!(
+            config.language_for(Some("example.com"), Some("")),
+            Some("en".to_string())
+        );
+
+        config.enabled = false;
+        assert!(!config.enabled_for(Some("de.example.com")));
+    }
+}
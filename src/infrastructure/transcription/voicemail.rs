@@ -0,0 +1,442 @@
+//! Background transcription of deposited voicemail
+
+use super::{TranscriptionConfig, TranscriptionProvider};
+use crate::domain::user::UserRepository;
+use crate::domain::voicemail::{
+    VoicemailMailbox, VoicemailMessage, VoicemailRepository, VoicemailStatus,
+};
+use async_trait::async_trait;
+use std::path::Path;
+use std::sync::Arc;
+use tokio::task::JoinHandle;
+use tracing::{debug, error, info, warn};
+use uuid::Uuid;
+
+/// Sends voicemail notification emails
+#[async_trait]
+pub trait VoicemailEmailSender: Send + Sync {
+    async fn send(&self, to: &str, subject: &str, body: &str) -> Result<(), String>;
+}
+
+/// Transcribes new messages and sends their email notification
+pub struct VoicemailTranscriber {
+    provider: Arc<dyn TranscriptionProvider>,
+    voicemail: Arc<dyn VoicemailRepository>,
+    config: TranscriptionConfig,
+    /// Mailbox owners, for their tenant and locale
+    users: Option<Arc<dyn UserRepository>>,
+    email: Option<Arc<dyn VoicemailEmailSender>>,
+}
+
+impl VoicemailTranscriber {
+    pub fn new(
+        provider: Arc<dyn TranscriptionProvider>,
+        voicemail: Arc<dyn VoicemailRepository>,
+        config: TranscriptionConfig,
+    ) -> Self {
+        Self {
+            provider,
+            voicemail,
+            config,
+            users: None,
+            email: None,
+        }
+    }
+
+    /// Look up mailbox owners for per-tenant settings and the language hint
+    pub fn with_user_repository(mut self, users: Arc<dyn UserRepository>) -> Self {
+        self.users = Some(users);
+        self
+    }
+
+    /// Email mailboxes with notifications enabled
+    pub fn with_email_sender(mut self, email: Arc<dyn VoicemailEmailSender>) -> Self {
+        self.email = Some(email);
+        self
+    }
+
+    /// Process a deposited message in the background
+    pub fn spawn(self: Arc<Self>, message: VoicemailMessage) -> JoinHandle<()> {
+        tokio::spawn(async move {
+            self.process(message).await;
+        })
+    }
+
+    /// Transcribe and store the transcript (when enabled for the owner's
+    /// tenant), then send the email notification
+    ///
+    /// Returns the message as notified; without a transcript when
+    /// transcription is off or failed.
+    pub async fn process(&self, mut message: VoicemailMessage) -> VoicemailMessage {
+        let mailbox = match self.voicemail.get_mailbox(&message.mailbox_id).await {
+            Ok(mailbox) => mailbox,
+            Err(e) => {
+                warn!(
+                    "Failed to get voicemail mailbox {}: {}",
+                    message.mailbox_id, e
+                );
+                None
+            }
+        };
+        let (realm, locale) = self.owner(&message.mailbox_id, mailbox.as_ref()).await;
+
+        if self.config.enabled_for(realm.as_deref()) {
+            let language = self
+                .config
+                .language_for(realm.as_deref(), locale.as_deref());
+            match self
+                .provider
+                .transcribe(Path::new(&message.audio_file_path), language.as_deref())
+                .await
+            {
+                Ok(transcript) => {
+                    match self
+                        .voicemail
+                        .set_transcript(message.id, &transcript.text, transcript.confidence)
+                        .await
+                    {
+                        Ok(()) => info!(
+                            "Transcribed voicemail {} ({} chars)",
+                            message.id,
+                            transcript.text.len()
+                        ),
+                        Err(e) => error!(
+                            "Failed to store transcript of voicemail {}: {}",
+                            message.id, e
+                        ),
+                    }
+                    message.transcript = Some(transcript.text);
+                    message.transcript_confidence = transcript.confidence;
+                }
+                Err(e) => warn!(
+                    "Voicemail {} not transcribed, notifying without transcript: {}",
+                    message.id, e
+                ),
+            }
+        }
+
+        self.notify(&message, mailbox.as_ref()).await;
+        message
+    }
+
+    /// Realm and locale of a mailbox's owner
+    async fn owner(
+        &self,
+        mailbox_id: &str,
+        mailbox: Option<&VoicemailMailbox>,
+    ) -> (Option<String>, Option<String>) {
+        let Some(users) = &self.users else {
+            return (None, None);
+        };
+        let user = match mailbox {
+            Some(mailbox) => users.find_by_id(mailbox.user_id).await,
+            None => users.find_by_username(mailbox_id).await,
+        };
+        match user {
+            Ok(Some(user)) => (Some(user.realm), user.locale),
+            Ok(None) => (None, None),
+            Err(e) => {
+                warn!("Failed to look up owner of mailbox {}: {}", mailbox_id, e);
+                (None, None)
+            }
+        }
+    }
+
+    async fn notify(&self, message: &VoicemailMessage, mailbox: Option<&VoicemailMailbox>) {
+        let (Some(email), Some(mailbox)) = (&self.email, mailbox) else {
+            return;
+        };
+        let Some(address) = mailbox
+            .email_address
+            .as_deref()
+            .filter(|_| mailbox.email_notification)
+        else {
+            return;
+        };
+        let (subject, body) = message.notification_email();
+        match email.send(address, &subject, &body).await {
+            Ok(()) => debug!("Emailed voicemail {} to {}", message.id, address),
+            Err(e) => error!(
+                "Failed to email voicemail {} to {}: {}",
+                message.id, address, e
+            ),
+        }
+    }
+}
+
+/// Voicemail repository that transcribes every deposited message
+pub struct TranscribingVoicemailRepository {
+    inner: Arc<dyn VoicemailRepository>,
+    transcriber: Arc<VoicemailTranscriber>,
+}
+
+impl TranscribingVoicemailRepository {
+    pub fn new(
+        inner: Arc<dyn VoicemailRepository>,
+        transcriber: Arc<VoicemailTranscriber>,
+    ) -> Self {
+        Self { inner, transcriber }
+    }
+}
+
+#[async_trait]
+impl VoicemailRepository for TranscribingVoicemailRepository {
+    async fn create_message(&self, message: VoicemailMessage) -> Result<VoicemailMessage, String> {
+        let message = self.inner.create_message(message).await?;
+        self.transcriber.clone().spawn(message.clone());
+        Ok(message)
+    }
+
+    async fn get_message(&self, id: Uuid) -> Result<Option<VoicemailMessage>, String> {
+        self.inner.get_message(id).await
+    }
+
+    async fn list_messages(
+        &self,
+        mailbox_id: &str,
+        status: Option<VoicemailStatus>,
+    ) -> Result<Vec<VoicemailMessage>, String> {
+        self.inner.list_messages(mailbox_id, status).await
+    }
+
+    async fn update_message_status(&self, id: Uuid, status: VoicemailStatus) -> Result<(), String> {
+        self.inner.update_message_status(id, status).await
+    }
+
+    async fn set_transcript(
+        &self,
+        id: Uuid,
+        transcript: &str,
+        confidence: Option<f32>,
+    ) -> Result<(), String> {
+        self.inner.set_transcript(id, transcript, confidence).await
+    }
+
+    async fn delete_message(&self, id: Uuid) -> Result<(), String> {
+        self.inner.delete_message(id).await
+    }
+
+    async fn count_messages(
+        &self,
+        mailbox_id: &str,
+        status: Option<VoicemailStatus>,
+    ) -> Result<u32, String> {
+        self.inner.count_messages(mailbox_id, status).await
+    }
+
+    async fn count_audio_references(&self, audio_file_path: &str) -> Result<u32, String> {
+        self.inner.count_audio_references(audio_file_path).await
+    }
+
+    async fn get_mailbox(&self, mailbox_id: &str) -> Result<Option<VoicemailMailbox>, String> {
+        self.inner.get_mailbox(mailbox_id).await
+    }
+
+    async fn save_mailbox(&self, mailbox: VoicemailMailbox) -> Result<VoicemailMailbox, String> {
+        self.inner.save_mailbox(mailbox).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::domain::user::repository::MockUserRepository;
+    use crate::domain::user::User;
+    use crate::infrastructure::persistence::MemoryVoicemailRepository;
+    use crate::infrastructure::transcription::{TenantTranscription, Transcript};
+    use std::sync::Mutex;
+
+    /// Provider answering with a fixed transcript (or failing) and
+    /// recording the language hints it got
+    struct FakeProvider {
+        result: Result<Transcript, String>,
+        languages: Mutex<Vec<Option<String>>>,
+    }
+
+    #[async_trait]
+    impl TranscriptionProvider for FakeProvider {
+        async fn transcribe(
+            &self,
+            _wav: &Path,
+            language: Option<&str>,
+        ) -> Result<Transcript, String> {
+            self.languages
+                .lock()
+                .unwrap()
+                .push(language.map(str::to_string));
+            self.result.clone()
+        }
+    }
+
+    #[derive(Default)]
+    struct Outbox(Mutex<Vec<(String, String, String)>>);
+
+    #[async_trait]
+    impl VoicemailEmailSender for Outbox {
+        async fn send(&self, to: &str, subject: &str, body: &str) -> Result<(), String> {
+            self.0
+                .lock()
+                .unwrap()
+                .push((to.to_string(), subject.to_string(), body.to_string()));
+            Ok(())
+        }
+    }
+
+    async fn setup(
+        result: Result<Transcript, String>,
+        config: TranscriptionConfig,
+    ) -> (
+        Arc<MemoryVoicemailRepository>,
+        Arc<FakeProvider>,
+        Arc<Outbox>,
+        VoicemailTranscriber,
+    ) {
+        let store = Arc::new(MemoryVoicemailRepository::new());
+        let mut mailbox = VoicemailMailbox::new("alice".to_string(), 1);
+        mailbox.email_notification = true;
+        mailbox.email_address = Some("alice@example.com".to_string());
+        store.save_mailbox(mailbox).await.unwrap();
+        let provider = Arc::new(FakeProvider {
+            result,
+            languages: Mutex::new(Vec::new()),
+        });
+        let outbox = Arc::new(Outbox::default());
+        let transcriber = VoicemailTranscriber::new(provider.clone(), store.clone(), config)
+            .with_email_sender(outbox.clone());
+        (store, provider, outbox, transcriber)
+    }
+
+    fn voicemail() -> VoicemailMessage {
+        VoicemailMessage::new(
+            "alice".to_string(),
+            "sip:bob@example.com".to_string(),
+            Some("Bob".to_string()),
+            42,
+            "/var/voicemail/alice/msg001.wav".to_string(),
+            "wav".to_string(),
+        )
+    }
+
+    /// Alice (ID 1) in example.com, German locale
+    fn alice_directory() -> MockUserRepository {
+        let mut users = MockUserRepository::new();
+        users.expect_find_by_id().returning(|id| {
+            Ok((id == 1).then(|| User {
+                id: 1,
+                username: "alice".to_string(),
+                password_hash: String::new(),
+                sip_ha1: None,
+                realm: "example.com".to_string(),
+                display_name: None,
+                email: None,
+                department: None,
+                locale: Some("de-DE".to_string()),
+                timezone: None,
+                enabled: true,
+                role_id: None,
+                created_at: chrono::Utc::now(),
+                updated_at: chrono::Utc::now(),
+            }))
+        });
+        users
+    }
+
+    fn enabled() -> TranscriptionConfig {
+        TranscriptionConfig {
+            enabled: true,
+            default_language: Some("en-US".to_string()),
+            ..Default::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn test_deposit_stores_transcript_and_emails_it() {
+        let transcript = Transcript {
+            text: "Hi Alice, call me back".to_string(),
+            confidence: Some(0.91),
+            language: Some("en".to_string()),
+        };
+        let (store, provider, outbox, transcriber) = setup(Ok(transcript), enabled()).await;
+        let repository = TranscribingVoicemailRepository::new(store.clone(), Arc::new(transcriber));
+
+        let message = repository.create_message(voicemail()).await.unwrap();
+        // The deposit returns before the transcription finished
+        assert!(message.transcript.is_none());
+
+        for _ in 0..50 {
+            if !outbox.0.lock().unwrap().is_empty() {
+                break;
+            }
+            tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+        }
+        let stored = store.get_message(message.id).await.unwrap().unwrap();
+        assert_eq!(stored.transcript.as_deref(), Some("Hi Alice, call me back"));
+        assert_eq!(stored.transcript_confidence, Some(0.91));
+        assert_eq!(
+            provider.languages.lock().unwrap().as_slice(),
+            [Some("en-US".to_string())]
+        );
+
+        let sent = outbox.0.lock().unwrap();
+        assert_eq!(sent.len(), 1);
+        assert_eq!(sent[0].0, "alice@example.com");
+        assert!(sent[0].2.contains("Hi Alice, call me back"));
+    }
+
+    #[tokio::test]
+    async fn test_provider_failure_still_notifies() {
+        let (store, _provider, outbox, transcriber) =
+            setup(Err("connection refused".to_string()), enabled()).await;
+        let message = store.create_message(voicemail()).await.unwrap();
+
+        let notified = transcriber.process(message.clone()).await;
+
+        assert!(notified.transcript.is_none());
+        assert!(store
+            .get_message(message.id)
+            .await
+            .unwrap()
+            .unwrap()
+            .transcript
+            .is_none());
+        let sent = outbox.0.lock().unwrap();
+        assert_eq!(sent.len(), 1);
+        assert!(sent[0].2.contains("No transcript"));
+    }
+
+    #[tokio::test]
+    async fn test_owner_locale_and_tenant_flag() {
+        let transcript = Transcript {
+            text: "Hallo Alice".to_string(),
+            confidence: None,
+            language: None,
+        };
+        let (store, provider, _outbox, transcriber) =
+            setup(Ok(transcript.clone()), enabled()).await;
+        let transcriber = transcriber.with_user_repository(Arc::new(alice_directory()));
+        let message = store.create_message(voicemail()).await.unwrap();
+
+        // The owner's locale wins over the default language
+        transcriber.process(message.clone()).await;
+        assert_eq!(
+            provider.languages.lock().unwrap().as_slice(),
+            [Some("de-DE".to_string())]
+        );
+
+        let mut config = enabled();
+        config.tenants.insert(
+            "example.com".to_string(),
+            TenantTranscription {
+                enabled: false,
+                language: None,
+            },
+        );
+        let (store, provider, outbox, transcriber) = setup(Ok(transcript), config).await;
+        let transcriber = transcriber.with_user_repository(Arc::new(alice_directory()));
+        let message = store.create_message(voicemail()).await.unwrap();
+
+        transcriber.process(message).await;
+        assert!(provider.languages.lock().unwrap().is_empty());
+        assert_eq!(outbox.0.lock().unwrap().len(), 1);
+    }
+}
@@ -0,0 +1,23 @@
+//! Queue agent state API handlers
+
+use super::cdr_dto::ApiResponse;
+use super::queue_callback_handler::queue_engine;
+use super::user_handler::AppState;
+use axum::{
+    extract::{Path, State},
+    response::{IntoResponse, Response},
+    Json,
+};
+
+/// Recent availability changes of an agent (by user ID), oldest first
+pub async fn get_agent_state_history(
+    State(state): State<AppState>,
+    Path(id): Path<i32>,
+) -> Response {
+    let engine = match queue_engine(&state) {
+        Ok(engine) => engine,
+        Err(response) => return response,
+    };
+
+    Json(ApiResponse::success(engine.agent_state_history(id))).into_response()
+}
@@ -0,0 +1,156 @@
+//! Announcement route API handlers
+//!
+//! Announcement-only numbers live under `/api/routing/announcements`. A
+//! route plays the message of the first matching time range (or its
+//! default message) `repeat` times, then hangs up or transfers the caller
+//! to voicemail or a forward destination.
+
+use super::cdr_dto::ApiResponse;
+use super::user_handler::AppState;
+use crate::domain::routing::{
+    AnnouncementError, AnnouncementRoute, AnnouncementService, FollowUp, TimedMessage,
+};
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    Json,
+};
+use serde::Deserialize;
+use std::sync::Arc;
+use tracing::{error, info};
+use uuid::Uuid;
+
+/// Create/update announcement route request
+#[derive(Debug, Deserialize)]
+pub struct AnnouncementRouteRequest {
+    pub tenant: String,
+    pub number: String,
+    #[serde(default)]
+    pub messages: Vec<TimedMessage>,
+    pub default_audio_file: String,
+    #[serde(default = "default_repeat")]
+    pub repeat: u32,
+    #[serde(default)]
+    pub follow_up: FollowUp,
+}
+
+fn default_repeat() -> u32 {
+    1
+}
+
+impl AnnouncementRouteRequest {
+    fn into_route(self) -> AnnouncementRoute {
+        AnnouncementRoute {
+            messages: self.messages,
+            repeat: self.repeat,
+            follow_up: self.follow_up,
+            ..AnnouncementRoute::new(self.tenant, self.number, self.default_audio_file)
+        }
+    }
+}
+
+fn service_unavailable() -> Response {
+    error!("Announcement service not available");
+    (
+        StatusCode::SERVICE_UNAVAILABLE,
+        Json(ApiResponse::<()>::error(
+            "Announcement routes not available".to_string(),
+        )),
+    )
+        .into_response()
+}
+
+#[allow(clippy::result_large_err)]
+fn service(state: &AppState) -> Result<&Arc<AnnouncementService>, Response> {
+    state.announcements.as_ref().ok_or_else(service_unavailable)
+}
+
+fn error_response(e: AnnouncementError) -> Response {
+    let status = match e {
+        AnnouncementError::Invalid(_) => StatusCode::BAD_REQUEST,
+        AnnouncementError::NotFound(_) => StatusCode::NOT_FOUND,
+        AnnouncementError::Duplicate(_) => StatusCode::CONFLICT,
+        AnnouncementError::Repository(ref e) => {
+            error!("API: Announcement route database error: {}", e);
+            return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+        }
+    };
+    (status, Json(ApiResponse::<()>::error(e.to_string()))).into_response()
+}
+
+/// List announcement routes
+pub async fn list_announcement_routes(State(state): State<AppState>) -> Response {
+    let service = match service(&state) {
+        Ok(service) => service,
+        Err(response) => return response,
+    };
+    match service.list().await {
+        Ok(routes) => Json(ApiResponse::success(routes)).into_response(),
+        Err(e) => error_response(e),
+    }
+}
+
+/// Get an announcement route
+pub async fn get_announcement_route(
+    State(state): State<AppState>,
+    Path(id): Path<Uuid>,
+) -> Response {
+    let service = match service(&state) {
+        Ok(service) => service,
+        Err(response) => return response,
+    };
+    match service.get(id).await {
+        Ok(route) => Json(ApiResponse::success(route)).into_response(),
+        Err(e) => error_response(e),
+    }
+}
+
+/// Create an announcement route
+pub async fn create_announcement_route(
+    State(state): State<AppState>,
+    Json(req): Json<AnnouncementRouteRequest>,
+) -> Response {
+    let service = match service(&state) {
+        Ok(service) => service,
+        Err(response) => return response,
+    };
+    info!("API: Creating announcement route for {}@{}", req.number, req.tenant);
+    match service.create(req.into_route()).await {
+        Ok(route) => (StatusCode::CREATED, Json(ApiResponse::success(route))).into_response(),
+        Err(e) => error_response(e),
+    }
+}
+
+/// Replace an announcement route's settings
+pub async fn update_announcement_route(
+    State(state): State<AppState>,
+    Path(id): Path<Uuid>,
+    Json(req): Json<AnnouncementRouteRequest>,
+) -> Response {
+    let service = match service(&state) {
+        Ok(service) => service,
+        Err(response) => return response,
+    };
+    info!("API: Updating announcement route {}", id);
+    match service.update(id, req.into_route()).await {
+        Ok(route) => Json(ApiResponse::success(route)).into_response(),
+        Err(e) => error_response(e),
+    }
+}
+
+/// Delete an announcement route
+pub async fn delete_announcement_route(
+    State(state): State<AppState>,
+    Path(id): Path<Uuid>,
+) -> Response {
+    let service = match service(&state) {
+        Ok(service) => service,
+        Err(response) => return response,
+    };
+    info!("API: Deleting announcement route {}", id);
+    match service.delete(id).await {
+        Ok(()) => StatusCode::NO_CONTENT.into_response(),
+        Err(e) => error_response(e),
+    }
+}
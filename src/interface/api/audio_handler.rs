@@ -0,0 +1,318 @@
+//! Audio file management API handlers
+//!
+//! Upload, list and delete MOH / prompt / announcement files at runtime,
+//! and load or reload the MOH classes playing them.
+
+use super::cdr_dto::ApiResponse;
+use super::user_handler::AppState;
+use crate::domain::audio::{AudioCategory, AudioLibraryError, AudioReference, StoredAudio};
+use crate::infrastructure::media::MohClassConfig;
+use axum::{
+    extract::{Multipart, Path, Query, State},
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    Json,
+};
+use serde::{Deserialize, Serialize};
+use tracing::{error, info, warn};
+
+/// Maximum accepted upload size (request body limit for the audio routes)
+pub const MAX_AUDIO_UPLOAD_BYTES: usize = 20 * 1024 * 1024;
+
+/// Query parameters for listing audio files
+#[derive(Debug, Deserialize)]
+pub struct ListAudioQuery {
+    pub tenant_id: Option<String>,
+    pub category: Option<String>,
+}
+
+/// Query parameters for single-file operations
+#[derive(Debug, Deserialize)]
+pub struct AudioFileQuery {
+    pub tenant_id: Option<String>,
+}
+
+/// Audio file with its usage
+#[derive(Debug, Serialize, Deserialize)]
+pub struct AudioFileResponse {
+    #[serde(flatten)]
+    pub file: StoredAudio,
+    pub references: Vec<AudioReference>,
+}
+
+/// Body of a 409 response for a referenced file
+#[derive(Debug, Serialize, Deserialize)]
+pub struct AudioInUseResponse {
+    pub id: String,
+    pub references: Vec<AudioReference>,
+}
+
+fn library_unavailable() -> Response {
+    error!("Audio library not available");
+    (
+        StatusCode::SERVICE_UNAVAILABLE,
+        Json(ApiResponse::<()>::error("Audio library not available".to_string())),
+    )
+        .into_response()
+}
+
+/// Upload an audio file (multipart: `file`, `name`, `category`, optional `tenant_id`)
+pub async fn upload_audio(State(state): State<AppState>, mut multipart: Multipart) -> Response {
+    let library = match &state.audio_library {
+        Some(library) => library.clone(),
+        None => return library_unavailable(),
+    };
+
+    let mut data: Option<Vec<u8>> = None;
+    let mut name: Option<String> = None;
+    let mut category: Option<String> = None;
+    let mut tenant_id: Option<String> = None;
+
+    loop {
+        let field = match multipart.next_field().await {
+            Ok(Some(field)) => field,
+            Ok(None) => break,
+            Err(e) => {
+                warn!("API: Invalid multipart upload: {}", e);
+                return (
+                    StatusCode::BAD_REQUEST,
+                    Json(ApiResponse::<()>::error(format!("Invalid multipart body: {}", e))),
+                )
+                    .into_response();
+            }
+        };
+
+        let field_name = field.name().unwrap_or_default().to_string();
+        if field_name == "file" {
+            if name.is_none() {
+                name = field.file_name().map(|n| n.trim_end_matches(".wav").to_string());
+            }
+            match field.bytes().await {
+                Ok(bytes) => data = Some(bytes.to_vec()),
+                Err(e) => {
+                    return (
+                        StatusCode::BAD_REQUEST,
+                        Json(ApiResponse::<()>::error(format!("Failed to read file: {}", e))),
+                    )
+                        .into_response()
+                }
+            }
+        } else {
+            let value = field.text().await.unwrap_or_default();
+            match field_name.as_str() {
+                "name" => name = Some(value),
+                "category" => category = Some(value),
+                "tenant_id" if !value.is_empty() => tenant_id = Some(value),
+                _ => {}
+            }
+        }
+    }
+
+    let data = match data {
+        Some(data) => data,
+        None => {
+            return (
+                StatusCode::BAD_REQUEST,
+                Json(ApiResponse::<()>::error("Missing 'file' field".to_string())),
+            )
+                .into_response()
+        }
+    };
+
+    let category = match category.as_deref().map(str::parse::<AudioCategory>) {
+        Some(Ok(category)) => category,
+        _ => {
+            return (
+                StatusCode::BAD_REQUEST,
+                Json(ApiResponse::<()>::error(
+                    "Field 'category' must be one of: moh, prompt, announcement".to_string(),
+                )),
+            )
+                .into_response()
+        }
+    };
+
+    let name = name.unwrap_or_else(|| "untitled".to_string());
+    info!(
+        "API: Uploading audio '{}' ({}, {} bytes, tenant {:?})",
+        name,
+        category.as_str(),
+        data.len(),
+        tenant_id
+    );
+
+    // Decoding and resampling are CPU bound
+    let upload_tenant = tenant_id.clone();
+    let result = tokio::task::spawn_blocking(move || {
+        library.upload(upload_tenant.as_deref(), &name, category, &data)
+    })
+    .await;
+
+    match result {
+        Ok(Ok(file)) => {
+            let references = state
+                .audio_library
+                .as_ref()
+                .map(|l| l.references(tenant_id.as_deref(), &file.id))
+                .unwrap_or_default();
+            (
+                StatusCode::CREATED,
+                Json(ApiResponse::success(AudioFileResponse { file, references })),
+            )
+                .into_response()
+        }
+        Ok(Err(AudioLibraryError::Io(e))) => {
+            error!("API: Failed to store audio file: {}", e);
+            StatusCode::INTERNAL_SERVER_ERROR.into_response()
+        }
+        Ok(Err(e)) => (
+            StatusCode::UNPROCESSABLE_ENTITY,
+            Json(ApiResponse::<()>::error(e.to_string())),
+        )
+            .into_response(),
+        Err(e) => {
+            error!("API: Audio upload task failed: {}", e);
+            StatusCode::INTERNAL_SERVER_ERROR.into_response()
+        }
+    }
+}
+
+/// List audio files with metadata and usage references
+pub async fn list_audio(
+    State(state): State<AppState>,
+    Query(query): Query<ListAudioQuery>,
+) -> Response {
+    let library = match &state.audio_library {
+        Some(library) => library,
+        None => return library_unavailable(),
+    };
+
+    let category = match query.category.as_deref() {
+        Some(c) => match c.parse::<AudioCategory>() {
+            Ok(category) => Some(category),
+            Err(_) => {
+                return (
+                    StatusCode::BAD_REQUEST,
+                    Json(ApiResponse::<()>::error(format!("Unknown category: {}", c))),
+                )
+                    .into_response()
+            }
+        },
+        None => None,
+    };
+
+    let tenant_id = query.tenant_id.as_deref();
+    let files: Vec<AudioFileResponse> = library
+        .list(tenant_id, category)
+        .into_iter()
+        .map(|file| {
+            let references = library.references(tenant_id, &file.id);
+            AudioFileResponse { file, references }
+        })
+        .collect();
+
+    Json(ApiResponse::success(files)).into_response()
+}
+
+/// Delete an audio file; 409 if it is still referenced
+pub async fn delete_audio(
+    State(state): State<AppState>,
+    Path(id): Path<String>,
+    Query(query): Query<AudioFileQuery>,
+) -> Response {
+    let library = match &state.audio_library {
+        Some(library) => library,
+        None => return library_unavailable(),
+    };
+
+    info!("API: Deleting audio file {} (tenant {:?})", id, query.tenant_id);
+
+    match library.delete(query.tenant_id.as_deref(), &id) {
+        Ok(file) => Json(ApiResponse::success(file)).into_response(),
+        Err(AudioLibraryError::InUse(references)) => {
+            warn!("API: Audio file {} is still in use", id);
+            (
+                StatusCode::CONFLICT,
+                Json(ApiResponse {
+                    success: false,
+                    data: Some(AudioInUseResponse { id, references }),
+                    error: Some("Audio file is still referenced".to_string()),
+                }),
+            )
+                .into_response()
+        }
+        Err(AudioLibraryError::NotFound(_)) => (
+            StatusCode::NOT_FOUND,
+            Json(ApiResponse::<()>::error(format!("Audio file {} not found", id))),
+        )
+            .into_response(),
+        Err(e) => {
+            error!("API: Failed to delete audio file {}: {}", id, e);
+            StatusCode::INTERNAL_SERVER_ERROR.into_response()
+        }
+    }
+}
+
+fn moh_classes_unavailable() -> Response {
+    (
+        StatusCode::SERVICE_UNAVAILABLE,
+        Json(ApiResponse::<()>::error("MOH classes not available".to_string())),
+    )
+        .into_response()
+}
+
+/// MOH classes and their playlists
+pub async fn list_moh_classes(State(state): State<AppState>) -> Response {
+    match &state.moh_classes {
+        Some(classes) => Json(ApiResponse::success(classes.configs())).into_response(),
+        None => moh_classes_unavailable(),
+    }
+}
+
+/// Load a MOH class, or reload it with a new playlist; calls already on
+/// hold keep the playlist they started with
+pub async fn put_moh_class(
+    State(state): State<AppState>,
+    Path(name): Path<String>,
+    Json(config): Json<MohClassConfig>,
+) -> Response {
+    let classes = match &state.moh_classes {
+        Some(classes) => classes.clone(),
+        None => return moh_classes_unavailable(),
+    };
+
+    info!("API: Loading MOH class {} with {} files", name, config.files.len());
+    // Files are decoded on load
+    let loaded = config.clone();
+    let result = tokio::task::spawn_blocking(move || classes.load(&name, loaded)).await;
+    match result {
+        Ok(Ok(_)) => Json(ApiResponse::success(config)).into_response(),
+        Ok(Err(e)) => (
+            StatusCode::UNPROCESSABLE_ENTITY,
+            Json(ApiResponse::<()>::error(e)),
+        )
+            .into_response(),
+        Err(e) => {
+            error!("API: MOH class load task failed: {}", e);
+            StatusCode::INTERNAL_SERVER_ERROR.into_response()
+        }
+    }
+}
+
+/// Remove a MOH class; its files can be deleted afterwards
+pub async fn delete_moh_class(State(state): State<AppState>, Path(name): Path<String>) -> Response {
+    let classes = match &state.moh_classes {
+        Some(classes) => classes,
+        None => return moh_classes_unavailable(),
+    };
+    if classes.remove(&name) {
+        info!("API: Removed MOH class {}", name);
+        Json(ApiResponse::success(name)).into_response()
+    } else {
+        (
+            StatusCode::NOT_FOUND,
+            Json(ApiResponse::<()>::error(format!("MOH class {} not found", name))),
+        )
+            .into_response()
+    }
+}
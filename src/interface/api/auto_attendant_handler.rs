@@ -0,0 +1,176 @@
+//! Auto-attendant API handlers
+//!
+//! Auto-attendants live under `/api/routing/auto-attendants`. An attendant
+//! answers its numbers with the day or night greeting, runs the menu keys,
+//! transfers dialed extensions and offers a dial-by-name directory.
+
+use super::cdr_dto::ApiResponse;
+use super::user_handler::AppState;
+use crate::domain::call_forwarding::TimeRange;
+use crate::domain::routing::{
+    AttendantNumber, AttendantOption, AutoAttendant, AutoAttendantError, AutoAttendantService,
+};
+use crate::infrastructure::ivr::{DialByName, ExtensionCollection};
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    Json,
+};
+use serde::Deserialize;
+use std::sync::Arc;
+use tracing::{error, info};
+use uuid::Uuid;
+
+/// Create/update auto-attendant request
+#[derive(Debug, Deserialize)]
+pub struct AutoAttendantRequest {
+    pub tenant: String,
+    pub name: String,
+    #[serde(default)]
+    pub numbers: Vec<AttendantNumber>,
+    pub day_greeting: String,
+    #[serde(default)]
+    pub night_greeting: Option<String>,
+    #[serde(default)]
+    pub business_hours: Vec<TimeRange>,
+    #[serde(default)]
+    pub options: Vec<AttendantOption>,
+    /// Extension dialing settings; `null` turns extension dialing off
+    #[serde(default = "default_extension_dialing")]
+    pub extension_dialing: Option<ExtensionCollection>,
+    #[serde(default)]
+    pub dial_by_name: DialByName,
+    #[serde(default = "default_max_retries")]
+    pub max_retries: u32,
+}
+
+fn default_extension_dialing() -> Option<ExtensionCollection> {
+    Some(ExtensionCollection::default())
+}
+
+fn default_max_retries() -> u32 {
+    3
+}
+
+impl AutoAttendantRequest {
+    fn into_attendant(self) -> AutoAttendant {
+        AutoAttendant {
+            numbers: self.numbers,
+            night_greeting: self.night_greeting,
+            business_hours: self.business_hours,
+            options: self.options,
+            extension_dialing: self.extension_dialing,
+            dial_by_name: self.dial_by_name,
+            max_retries: self.max_retries,
+            ..AutoAttendant::new(self.tenant, self.name, self.day_greeting)
+        }
+    }
+}
+
+fn service_unavailable() -> Response {
+    error!("Auto-attendant service not available");
+    (
+        StatusCode::SERVICE_UNAVAILABLE,
+        Json(ApiResponse::<()>::error(
+            "Auto-attendants not available".to_string(),
+        )),
+    )
+        .into_response()
+}
+
+#[allow(clippy::result_large_err)]
+fn service(state: &AppState) -> Result<&Arc<AutoAttendantService>, Response> {
+    state.auto_attendants.as_ref().ok_or_else(service_unavailable)
+}
+
+fn error_response(e: AutoAttendantError) -> Response {
+    let status = match e {
+        AutoAttendantError::Invalid(_) => StatusCode::BAD_REQUEST,
+        AutoAttendantError::NotFound(_) => StatusCode::NOT_FOUND,
+        AutoAttendantError::Duplicate(_) => StatusCode::CONFLICT,
+        AutoAttendantError::Repository(ref e) => {
+            error!("API: Auto-attendant database error: {}", e);
+            return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+        }
+    };
+    (status, Json(ApiResponse::<()>::error(e.to_string()))).into_response()
+}
+
+/// List auto-attendants
+pub async fn list_auto_attendants(State(state): State<AppState>) -> Response {
+    let service = match service(&state) {
+        Ok(service) => service,
+        Err(response) => return response,
+    };
+    match service.list().await {
+        Ok(attendants) => Json(ApiResponse::success(attendants)).into_response(),
+        Err(e) => error_response(e),
+    }
+}
+
+/// Get an auto-attendant
+pub async fn get_auto_attendant(
+    State(state): State<AppState>,
+    Path(id): Path<Uuid>,
+) -> Response {
+    let service = match service(&state) {
+        Ok(service) => service,
+        Err(response) => return response,
+    };
+    match service.get(id).await {
+        Ok(attendant) => Json(ApiResponse::success(attendant)).into_response(),
+        Err(e) => error_response(e),
+    }
+}
+
+/// Create an auto-attendant
+pub async fn create_auto_attendant(
+    State(state): State<AppState>,
+    Json(req): Json<AutoAttendantRequest>,
+) -> Response {
+    let service = match service(&state) {
+        Ok(service) => service,
+        Err(response) => return response,
+    };
+    info!("API: Creating auto-attendant {} for {}", req.name, req.tenant);
+    match service.create(req.into_attendant()).await {
+        Ok(attendant) => {
+            (StatusCode::CREATED, Json(ApiResponse::success(attendant))).into_response()
+        }
+        Err(e) => error_response(e),
+    }
+}
+
+/// Replace an auto-attendant's settings
+pub async fn update_auto_attendant(
+    State(state): State<AppState>,
+    Path(id): Path<Uuid>,
+    Json(req): Json<AutoAttendantRequest>,
+) -> Response {
+    let service = match service(&state) {
+        Ok(service) => service,
+        Err(response) => return response,
+    };
+    info!("API: Updating auto-attendant {}", id);
+    match service.update(id, req.into_attendant()).await {
+        Ok(attendant) => Json(ApiResponse::success(attendant)).into_response(),
+        Err(e) => error_response(e),
+    }
+}
+
+/// Delete an auto-attendant
+pub async fn delete_auto_attendant(
+    State(state): State<AppState>,
+    Path(id): Path<Uuid>,
+) -> Response {
+    let service = match service(&state) {
+        Ok(service) => service,
+        Err(response) => return response,
+    };
+    info!("API: Deleting auto-attendant {}", id);
+    match service.delete(id).await {
+        Ok(()) => StatusCode::NO_CONTENT.into_response(),
+        Err(e) => error_response(e),
+    }
+}
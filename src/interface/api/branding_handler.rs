@@ -0,0 +1,66 @@
+//! Tenant branding API handlers
+//!
+//! Tenants are identified by their SIP realm, as in the audio library.
+
+use super::cdr_dto::ApiResponse;
+use super::user_handler::AppState;
+use crate::domain::tenant_branding::{BrandingPatch, BrandingRegistry};
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    Json,
+};
+use std::sync::Arc;
+use tracing::{error, info, warn};
+
+#[allow(clippy::result_large_err)]
+fn branding(state: &AppState) -> Result<&Arc<BrandingRegistry>, Response> {
+    state.branding.as_ref().ok_or_else(|| {
+        error!("Tenant branding not available");
+        (
+            StatusCode::SERVICE_UNAVAILABLE,
+            Json(ApiResponse::<()>::error(
+                "Tenant branding not enabled".to_string(),
+            )),
+        )
+            .into_response()
+    })
+}
+
+/// Branding of a tenant (empty when it uses the global defaults)
+pub async fn get_tenant_branding(
+    State(state): State<AppState>,
+    Path(id): Path<String>,
+) -> Response {
+    let branding = match branding(&state) {
+        Ok(branding) => branding,
+        Err(response) => return response,
+    };
+
+    Json(ApiResponse::success(branding.get(&id).unwrap_or_default())).into_response()
+}
+
+/// Change parts of a tenant's branding; a MOH file must exist in the
+/// audio library
+pub async fn update_tenant_branding(
+    State(state): State<AppState>,
+    Path(id): Path<String>,
+    Json(patch): Json<BrandingPatch>,
+) -> Response {
+    let branding = match branding(&state) {
+        Ok(branding) => branding,
+        Err(response) => return response,
+    };
+
+    match branding.update(&id, patch) {
+        Ok(updated) => {
+            info!("API: Branding of tenant {} updated", id);
+            Json(ApiResponse::success(updated)).into_response()
+        }
+        Err(e) => {
+            warn!("API: Rejected branding of tenant {}: {}", id, e);
+            (StatusCode::BAD_REQUEST, Json(ApiResponse::<()>::error(e))).into_response()
+        }
+    }
+}
@@ -0,0 +1,177 @@
+//! Per-call debug logging API handlers
+//!
+//! `POST /calls/:call_id/debug` captures every log event of an active call
+//! until it ends, `GET` on the same path returns the captured lines, and
+//! `POST /users/:id/debug?next_calls=1` arms the user's next calls before
+//! they start. `GET /api/admin/debug-targets` lists what is armed;
+//! credentials are checked as for diagnostics.
+
+use super::cdr_dto::ApiResponse;
+use super::diagnostics_handler::{authorize, client_ip};
+use super::user_handler::AppState;
+use crate::infrastructure::call_debug::{CallDebugRegistry, DebugTargets, DebugUser};
+use axum::{
+    extract::{Path, Query, State},
+    http::{HeaderMap, StatusCode},
+    response::{IntoResponse, Response},
+    Json,
+};
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+use std::time::Duration;
+use tracing::{error, info};
+
+#[derive(Debug, Deserialize)]
+pub struct UserDebugQuery {
+    /// Number of the user's next calls to debug
+    #[serde(default = "default_next_calls")]
+    pub next_calls: u32,
+    /// How long the user stays armed (default from the configuration)
+    pub ttl_secs: Option<u64>,
+}
+
+fn default_next_calls() -> u32 {
+    1
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct CallDebugResponse {
+    pub call_id: String,
+    /// Whether the call is still being captured
+    pub armed: bool,
+    pub lines: Vec<String>,
+}
+
+#[allow(clippy::result_large_err)]
+fn call_debug(state: &AppState) -> Result<&Arc<CallDebugRegistry>, Response> {
+    state.call_debug.as_ref().ok_or_else(|| {
+        error!("Call debug logging not available");
+        (
+            StatusCode::SERVICE_UNAVAILABLE,
+            Json(ApiResponse::<()>::error(
+                "Call debug logging not available".to_string(),
+            )),
+        )
+            .into_response()
+    })
+}
+
+fn not_found(message: String) -> Response {
+    (
+        StatusCode::NOT_FOUND,
+        Json(ApiResponse::<()>::error(message)),
+    )
+        .into_response()
+}
+
+/// Capture all log events of an active call until it ends
+pub async fn enable_call_debug(
+    State(state): State<AppState>,
+    Path(call_id): Path<String>,
+) -> Response {
+    let registry = match call_debug(&state) {
+        Ok(registry) => registry,
+        Err(response) => return response,
+    };
+    let active = match &state.call_router {
+        Some(router) => router.get_active_call(&call_id).await.is_some(),
+        None => false,
+    };
+    if !active {
+        return not_found(format!("Call {} not found", call_id));
+    }
+
+    info!("API: Enabling debug logging for call {}", call_id);
+    registry.arm_call(&call_id);
+    Json(ApiResponse::success(CallDebugResponse {
+        lines: registry.lines(&call_id).unwrap_or_default(),
+        armed: true,
+        call_id,
+    }))
+    .into_response()
+}
+
+/// Lines captured for a call, while it runs or shortly after it ended
+pub async fn get_call_debug(
+    State(state): State<AppState>,
+    Path(call_id): Path<String>,
+) -> Response {
+    let registry = match call_debug(&state) {
+        Ok(registry) => registry,
+        Err(response) => return response,
+    };
+
+    match registry.lines(&call_id) {
+        Some(lines) => Json(ApiResponse::success(CallDebugResponse {
+            armed: registry.is_armed(&call_id),
+            call_id,
+            lines,
+        }))
+        .into_response(),
+        None => not_found(format!("No debug log for call {}", call_id)),
+    }
+}
+
+/// Debug a user's next calls
+pub async fn enable_user_debug(
+    State(state): State<AppState>,
+    Path(id): Path<i32>,
+    Query(query): Query<UserDebugQuery>,
+) -> Response {
+    let registry = match call_debug(&state) {
+        Ok(registry) => registry,
+        Err(response) => return response,
+    };
+    let user = match state.user_repository.find_by_id(id).await {
+        Ok(Some(user)) => user,
+        Ok(None) => return not_found(format!("User {} not found", id)),
+        Err(e) => {
+            error!("API: Failed to get user: {}", e);
+            return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+        }
+    };
+    if query.next_calls == 0 {
+        return (
+            StatusCode::BAD_REQUEST,
+            Json(ApiResponse::<()>::error(
+                "next_calls must be at least 1".to_string(),
+            )),
+        )
+            .into_response();
+    }
+
+    let armed: DebugUser = registry.arm_user(
+        &user.username,
+        query.next_calls,
+        query.ttl_secs.map(Duration::from_secs),
+    );
+    Json(ApiResponse::success(armed)).into_response()
+}
+
+/// Calls and users with debug logging enabled (requires `system:config`)
+pub async fn list_debug_targets(State(state): State<AppState>, headers: HeaderMap) -> Response {
+    let registry = match call_debug(&state) {
+        Ok(registry) => registry.clone(),
+        Err(response) => return response,
+    };
+    let Some(diagnostics) = state.diagnostics.clone() else {
+        error!("Admin authentication not available");
+        return (
+            StatusCode::SERVICE_UNAVAILABLE,
+            Json(ApiResponse::<()>::error(
+                "Admin authentication not available".to_string(),
+            )),
+        )
+            .into_response();
+    };
+
+    let ip = client_ip(&headers);
+    if let Err(response) =
+        authorize(&state, &diagnostics, &headers, &ip, "debug_targets", "list").await
+    {
+        return response;
+    }
+
+    let targets: DebugTargets = registry.targets();
+    Json(ApiResponse::success(targets)).into_response()
+}
@@ -0,0 +1,393 @@
+//! Per-user call history API handlers
+//!
+//! Compact recent-calls list for phone displays, distinct from the raw CDR
+//! listing. Supports ETag / If-None-Match so phones polling every few seconds
+//! get cheap 304 responses. Times are in the user's time zone unless the
+//! request asks for another with `tz`.
+
+use super::cdr_dto::ApiResponse;
+use super::timezone::{zone_header, TimeZoneContext};
+use super::user_handler::AppState;
+use crate::domain::call_history::{
+    build_call_history, contact_name, history_etag, CallDisposition, CallHistoryEntry,
+    HistoryDirection,
+};
+use crate::domain::shared::time_zone::in_zone;
+use crate::domain::speed_dial::effective_speed_dials;
+use axum::{
+    extract::{Path, Query, State},
+    http::{header, HeaderMap, StatusCode},
+    response::{IntoResponse, Response},
+    Json,
+};
+use chrono::{DateTime, FixedOffset};
+use chrono_tz::Tz;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use tracing::{error, info};
+use uuid::Uuid;
+
+/// Query parameters for call history
+#[derive(Debug, Deserialize)]
+pub struct CallHistoryQuery {
+    #[serde(default = "default_limit")]
+    pub limit: usize,
+}
+
+fn default_limit() -> usize {
+    50
+}
+
+/// Maximum number of history entries a single request may ask for
+const MAX_HISTORY_LIMIT: usize = 200;
+
+/// One logical call of the history
+#[derive(Debug, Serialize, Deserialize)]
+pub struct CallHistoryItem {
+    pub id: Uuid,
+    pub direction: HistoryDirection,
+    pub remote_number: String,
+    pub remote_name: Option<String>,
+    pub start_time: DateTime<FixedOffset>,
+    /// Talk time in seconds
+    pub duration: i32,
+    pub disposition: CallDisposition,
+    pub call_ids: Vec<String>,
+}
+
+impl CallHistoryItem {
+    /// `entry` with its start time in `zone`
+    pub fn in_zone(entry: CallHistoryEntry, zone: Tz) -> Self {
+        Self {
+            id: entry.id,
+            direction: entry.direction,
+            remote_number: entry.remote_number,
+            remote_name: entry.remote_name,
+            start_time: in_zone(entry.start_time, zone),
+            duration: entry.duration,
+            disposition: entry.disposition,
+            call_ids: entry.call_ids,
+        }
+    }
+}
+
+/// Call history response
+#[derive(Debug, Serialize, Deserialize)]
+pub struct CallHistoryResponse {
+    pub entries: Vec<CallHistoryItem>,
+    pub missed_since_last_read: usize,
+    pub last_read_at: Option<DateTime<FixedOffset>>,
+    /// Time zone of the times
+    pub timezone: String,
+}
+
+/// Mark-as-read response
+#[derive(Debug, Serialize, Deserialize)]
+pub struct MarkReadResponse {
+    pub last_read_at: DateTime<FixedOffset>,
+}
+
+/// Get a user's call history
+pub async fn get_call_history(
+    State(state): State<AppState>,
+    Path(id): Path<i32>,
+    Query(query): Query<CallHistoryQuery>,
+    tz: TimeZoneContext,
+    headers: HeaderMap,
+) -> Response {
+    info!("API: Getting call history for user ID: {} (limit: {})", id, query.limit);
+
+    let cdr_repo = match &state.cdr_repository {
+        Some(repo) => repo,
+        None => {
+            error!("CDR repository not available");
+            return StatusCode::SERVICE_UNAVAILABLE.into_response();
+        }
+    };
+
+    let user = match state.user_repository.find_by_id(id).await {
+        Ok(Some(user)) => user,
+        Ok(None) => {
+            return (
+                StatusCode::NOT_FOUND,
+                Json(ApiResponse::<()>::error(format!("User {} not found", id))),
+            )
+                .into_response()
+        }
+        Err(e) => {
+            error!("API: Failed to get user: {}", e);
+            return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+        }
+    };
+
+    let limit = query.limit.clamp(1, MAX_HISTORY_LIMIT);
+
+    // Fetch extra legs so that grouped chains still fill the requested limit
+    let cdrs = match cdr_repo.list_for_user(&user.username, (limit * 3) as i64).await {
+        Ok(cdrs) => cdrs,
+        Err(e) => {
+            error!("API: Failed to list CDRs for call history: {}", e);
+            return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+        }
+    };
+
+    let mut entries = build_call_history(&user.username, &cdrs, limit);
+
+    // External numbers are named after the user's labelled speed dials,
+    // matching numbers however they were entered
+    let contacts: Vec<(String, String)> = match (&state.numbering, &state.speed_dial_repository) {
+        (Some(_), Some(repo)) => {
+            let personal = repo.list_for_user(user.id).await.unwrap_or_default();
+            let company = repo.list_company().await.unwrap_or_default();
+            effective_speed_dials(&personal, &company)
+                .into_iter()
+                .filter_map(|s| s.label.map(|label| (label, s.target)))
+                .collect()
+        }
+        _ => Vec::new(),
+    };
+
+    // Resolve internal remote parties against the user directory
+    let mut names: HashMap<String, Option<String>> = HashMap::new();
+    for entry in entries.iter_mut() {
+        if !names.contains_key(&entry.remote_number) {
+            let mut name = match state.user_repository.find_by_username(&entry.remote_number).await {
+                Ok(Some(remote)) => remote.display_name,
+                _ => None,
+            };
+            if name.is_none() {
+                if let Some(plan) = &state.numbering {
+                    name = contact_name(&entry.remote_number, &contacts, plan, Some(&user.realm))
+                        .map(str::to_string);
+                }
+            }
+            names.insert(entry.remote_number.clone(), name);
+        }
+        entry.remote_name = names.get(&entry.remote_number).cloned().flatten();
+    }
+
+    let (missed_since_last_read, last_read_at) = match &state.missed_call_tracker {
+        Some(tracker) => (
+            tracker.missed_since_last_read(&user.username, &entries),
+            tracker.last_read(&user.username),
+        ),
+        None => (
+            entries
+                .iter()
+                .filter(|e| e.is_missed() && e.direction == HistoryDirection::Incoming)
+                .count(),
+            None,
+        ),
+    };
+
+    // The same calls in another time zone are another representation
+    let zone = tz.zone_for_user(&user);
+    let etag = history_etag(&entries, missed_since_last_read);
+    let etag = format!("\"{}-{}\"", etag.trim_matches('"'), zone.name());
+
+    let not_modified = headers
+        .get(header::IF_NONE_MATCH)
+        .and_then(|v| v.to_str().ok())
+        .map(|v| v.split(',').any(|tag| tag.trim() == etag || tag.trim() == "*"))
+        .unwrap_or(false);
+
+    if not_modified {
+        return (StatusCode::NOT_MODIFIED, [(header::ETAG, etag)]).into_response();
+    }
+
+    (
+        [(header::ETAG, etag)],
+        zone_header(zone),
+        Json(ApiResponse::success(CallHistoryResponse {
+            entries: entries
+                .into_iter()
+                .map(|entry| CallHistoryItem::in_zone(entry, zone))
+                .collect(),
+            missed_since_last_read,
+            last_read_at: last_read_at.map(|time| in_zone(time, zone)),
+            timezone: zone.name().to_string(),
+        })),
+    )
+        .into_response()
+}
+
+/// Mark a user's missed calls as read
+pub async fn mark_call_history_read(
+    State(state): State<AppState>,
+    Path(id): Path<i32>,
+    tz: TimeZoneContext,
+) -> Response {
+    info!("API: Marking missed calls as read for user ID: {}", id);
+
+    let tracker = match &state.missed_call_tracker {
+        Some(tracker) => tracker,
+        None => {
+            error!("Missed call tracker not available");
+            return StatusCode::SERVICE_UNAVAILABLE.into_response();
+        }
+    };
+
+    let user = match state.user_repository.find_by_id(id).await {
+        Ok(Some(user)) => user,
+        Ok(None) => {
+            return (
+                StatusCode::NOT_FOUND,
+                Json(ApiResponse::<()>::error(format!("User {} not found", id))),
+            )
+                .into_response()
+        }
+        Err(e) => {
+            error!("API: Failed to get user: {}", e);
+            return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+        }
+    };
+
+    let last_read_at = in_zone(tracker.mark_read(&user.username), tz.zone_for_user(&user));
+
+    Json(ApiResponse::success(MarkReadResponse { last_read_at })).into_response()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::domain::cdr::{CallDetailRecord, CallDirection, MockCdrRepository};
+    use crate::domain::user::repository::MockUserRepository;
+    use crate::domain::user::User;
+    use crate::domain::call_history::MissedCallTracker;
+    use axum::{
+        body::Body,
+        http::Request,
+        routing::{get, post},
+        Router,
+    };
+    use chrono::{TimeZone, Utc};
+    use serde_json::Value;
+    use std::sync::Arc;
+    use tower::ServiceExt;
+
+    fn user(id: i32, username: &str, realm: &str, timezone: Option<&str>) -> User {
+        User {
+            id,
+            username: username.to_string(),
+            password_hash: String::new(),
+            sip_ha1: None,
+            realm: realm.to_string(),
+            display_name: None,
+            email: None,
+            department: None,
+            locale: None,
+            timezone: timezone.map(str::to_string),
+            enabled: true,
+            role_id: None,
+            created_at: Utc::now(),
+            updated_at: Utc::now(),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_history_falls_back_to_user_then_tenant_zone() {
+        let mut users = MockUserRepository::new();
+        users.expect_find_by_id().returning(|id| {
+            Ok(Some(match id {
+                1 => user(1, "alice", "example.com", Some("America/New_York")),
+                2 => user(2, "bob", "acme.example.com", None),
+                _ => user(3, "carol", "example.com", None),
+            }))
+        });
+        users.expect_find_by_username().returning(|_| Ok(None));
+
+        let mut cdrs = MockCdrRepository::new();
+        cdrs.expect_list_for_user().returning(|username, _| {
+            let mut cdr = CallDetailRecord::new(
+                "call-1".to_string(),
+                "15550100".to_string(),
+                "sip:15550100@example.com".to_string(),
+                "192.0.2.10".to_string(),
+                username.to_string(),
+                format!("sip:{}@example.com", username),
+                CallDirection::Inbound,
+            );
+            cdr.start_time = Utc.with_ymd_and_hms(2024, 7, 1, 12, 0, 0).unwrap();
+            Ok(vec![cdr])
+        });
+
+        let mut state = AppState::for_tests(Arc::new(users));
+        state.cdr_repository = Some(Arc::new(cdrs));
+        state
+            .time_zones
+            .tenants
+            .insert("acme.example.com".to_string(), "Asia/Tokyo".to_string());
+        let app = Router::new()
+            .route("/users/:id/call-history", get(get_call_history))
+            .with_state(state);
+
+        let history = |uri: &'static str| {
+            let app = app.clone();
+            async move {
+                let response = app
+                    .oneshot(Request::builder().uri(uri).body(Body::empty()).unwrap())
+                    .await
+                    .unwrap();
+                assert_eq!(response.status(), StatusCode::OK);
+                let zone = response.headers()["x-time-zone"].to_str().unwrap().to_string();
+                let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+                    .await
+                    .unwrap();
+                let json: Value = serde_json::from_slice(&body).unwrap();
+                assert_eq!(json["data"]["timezone"], zone.as_str());
+                (zone, json["data"]["entries"][0]["start_time"].clone())
+            }
+        };
+
+        // The user's own zone
+        assert_eq!(
+            history("/users/1/call-history").await,
+            ("America/New_York".to_string(), "2024-07-01T08:00:00-04:00".into())
+        );
+        // No zone of their own: their tenant's
+        assert_eq!(
+            history("/users/2/call-history").await,
+            ("Asia/Tokyo".to_string(), "2024-07-01T21:00:00+09:00".into())
+        );
+        // Neither: the default, a zero offset written as Z
+        assert_eq!(
+            history("/users/3/call-history").await,
+            ("UTC".to_string(), "2024-07-01T12:00:00Z".into())
+        );
+        // tz wins over the user's zone
+        assert_eq!(
+            history("/users/1/call-history?tz=Europe/Berlin").await,
+            ("Europe/Berlin".to_string(), "2024-07-01T14:00:00+02:00".into())
+        );
+    }
+
+    #[tokio::test]
+    async fn test_mark_read_unknown_user_is_not_found() {
+        let mut users = MockUserRepository::new();
+        users.expect_find_by_id().returning(|id| {
+            Ok((id == 1).then(|| user(1, "alice", "example.com", None)))
+        });
+        users.expect_find_by_username().returning(|_| Ok(None));
+
+        let mut state = AppState::for_tests(Arc::new(users));
+        state.missed_call_tracker = Some(Arc::new(MissedCallTracker::new()));
+        let app = Router::new()
+            .route("/users/:id/call-history/read", post(mark_call_history_read))
+            .with_state(state);
+
+        let mark_read = |uri: &'static str| {
+            let app = app.clone();
+            async move {
+                app.oneshot(Request::post(uri).body(Body::empty()).unwrap())
+                    .await
+                    .unwrap()
+                    .status()
+            }
+        };
+        assert_eq!(mark_read("/users/1/call-history/read").await, StatusCode::OK);
+        assert_eq!(
+            mark_read("/users/2/call-history/read").await,
+            StatusCode::NOT_FOUND
+        );
+    }
+}
@@ -1,6 +1,7 @@
 /// Call Queue management REST API
 use crate::domain::call_queue::{
-    AgentStatus, CallQueue, CallQueueRepository, OverflowAction, QueueMember, QueueStrategy,
+    AgentStatus, CallQueue, CallQueueRepository, OverflowAction, OverflowRule, QueueMember,
+    QueueStrategy,
 };
 use axum::{
     extract::{Path, State},
@@ -57,6 +58,8 @@ struct CreateQueueRequest {
     ring_timeout_secs: Option<u64>,
     announce_position: Option<bool>,
     music_on_hold: Option<String>,
+    /// Checked in order; the first matching rule sends the call away
+    overflow_rules: Option<Vec<OverflowRule>>,
 }
 
 /// Request to update a call queue
@@ -69,6 +72,8 @@ struct UpdateQueueRequest {
     ring_timeout_secs: Option<u64>,
     announce_position: Option<bool>,
     music_on_hold: Option<String>,
+    /// Checked in order; the first matching rule sends the call away
+    overflow_rules: Option<Vec<OverflowRule>>,
 }
 
 /// Response for call queue operations
@@ -83,6 +88,7 @@ struct QueueResponse {
     ring_timeout_secs: u64,
     announce_position: bool,
     music_on_hold: Option<String>,
+    overflow_rules: Vec<OverflowRule>,
     created_at: String,
     updated_at: String,
 }
@@ -99,6 +105,7 @@ impl From<CallQueue> for QueueResponse {
             ring_timeout_secs: queue.ring_timeout.as_secs(),
             announce_position: queue.announce_position,
             music_on_hold: queue.music_on_hold,
+            overflow_rules: queue.overflow_rules,
             created_at: queue.created_at.to_rfc3339(),
             updated_at: queue.updated_at.to_rfc3339(),
         }
@@ -218,6 +225,9 @@ async fn create_queue(
     if let Some(music_on_hold) = req.music_on_hold {
         queue.music_on_hold = Some(music_on_hold);
     }
+    if let Some(overflow_rules) = req.overflow_rules {
+        queue.overflow_rules = overflow_rules;
+    }
 
     match state.repository.create_queue(queue).await {
         Ok(queue) => {
@@ -369,6 +379,9 @@ async fn update_queue(
     if let Some(music_on_hold) = req.music_on_hold {
         queue.music_on_hold = Some(music_on_hold);
     }
+    if let Some(overflow_rules) = req.overflow_rules {
+        queue.overflow_rules = overflow_rules;
+    }
 
     queue.updated_at = chrono::Utc::now();
 
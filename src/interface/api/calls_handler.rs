@@ -1,18 +1,31 @@
 //! Call Management API handlers
 
 use super::cdr_dto::ApiResponse;
+use super::openapi::{boolean, date_time, integer, nullable, object, string, ApiSchema};
+use super::pagination::Pagination;
+use super::timezone::TimeZoneContext;
 use super::user_handler::AppState;
+use crate::domain::shared::time_zone::{in_zone, local_day_start};
+use crate::domain::shared::SortOrder;
+use crate::infrastructure::protocols::sip::ActiveCallInfo;
 use axum::{
     extract::{Path, State},
     http::StatusCode,
+    response::{IntoResponse, Response},
     Json,
 };
+use std::cmp::Ordering;
 use serde::{Deserialize, Serialize};
-use tracing::{error, info};
+use serde_json::{json, Value};
+use tracing::{error, info, Instrument, Span};
 
 /// Call statistics response
 #[derive(Debug, Serialize, Deserialize)]
 pub struct CallStatsResponse {
+    /// Time zone whose current day "today" is
+    pub timezone: String,
+    /// Start of today in that zone
+    pub today_start: chrono::DateTime<chrono::FixedOffset>,
     pub total_active_calls: usize,
     pub total_calls_today: i64,
     pub total_completed_calls: i64,
@@ -20,35 +33,108 @@ pub struct CallStatsResponse {
     pub average_call_duration: i32,
 }
 
-/// Active calls list response
-#[derive(Debug, Serialize, Deserialize)]
-pub struct ActiveCallsResponse {
-    pub calls: Vec<crate::infrastructure::protocols::sip::ActiveCallInfo>,
-    pub total: usize,
+impl ApiSchema for CallStatsResponse {
+    const NAME: &'static str = "CallStats";
+
+    fn schema() -> Value {
+        object(json!({
+            "timezone": string(),
+            "today_start": date_time(),
+            "total_active_calls": integer(),
+            "total_calls_today": integer(),
+            "total_completed_calls": integer(),
+            "total_failed_calls": integer(),
+            "average_call_duration": integer(),
+        }))
+    }
+}
+
+impl ApiSchema for ActiveCallInfo {
+    const NAME: &'static str = "ActiveCall";
+
+    fn schema() -> Value {
+        object(json!({
+            "call_id": string(),
+            "caller_uri": string(),
+            "callee_uri": string(),
+            "state": string(),
+            "duration": integer(),
+            "caller_contact": nullable(string()),
+            "callee_contact": nullable(string()),
+            "on_hold": boolean(),
+            "caller_ptime_ms": nullable(integer()),
+            "callee_ptime_ms": nullable(integer()),
+            "caller_rtcp_mux": nullable(boolean()),
+            "callee_rtcp_mux": nullable(boolean()),
+            "caller_sdp_rtp": nullable(string()),
+            "caller_latched_rtp": nullable(string()),
+            "callee_sdp_rtp": nullable(string()),
+            "callee_latched_rtp": nullable(string()),
+            "setup_secs": nullable(integer()),
+            "ring_secs": nullable(integer()),
+            "talk_secs": integer(),
+            "hold_secs": integer(),
+            "hold_count": integer(),
+            "moh": nullable(object(json!({
+                "class": string(),
+                "file_id": string(),
+                "name": string(),
+                "position_ms": integer(),
+            }))),
+        }))
+    }
+}
+
+/// Fields active calls can be sorted by
+const CALL_SORT_FIELDS: &[&str] = &["call_id", "duration", "caller_uri", "callee_uri"];
+
+/// Order of two calls by `sort`, ties broken by Call-ID
+fn compare_calls(a: &ActiveCallInfo, b: &ActiveCallInfo, sort: &SortOrder) -> Ordering {
+    let order = match sort.field.as_str() {
+        "duration" => a.duration.cmp(&b.duration),
+        "caller_uri" => a.caller_uri.cmp(&b.caller_uri),
+        "callee_uri" => a.callee_uri.cmp(&b.callee_uri),
+        _ => Ordering::Equal,
+    }
+    .then_with(|| a.call_id.cmp(&b.call_id));
+    if sort.descending {
+        order.reverse()
+    } else {
+        order
+    }
 }
 
 /// Get active calls
-pub async fn get_active_calls(
-    State(state): State<AppState>,
-) -> Result<Json<ApiResponse<ActiveCallsResponse>>, StatusCode> {
+pub async fn get_active_calls(State(state): State<AppState>, page: Pagination) -> Response {
     info!("API: Getting active calls");
 
     let call_router = match &state.call_router {
         Some(router) => router,
         None => {
             error!("Call router not available");
-            return Ok(Json(ApiResponse::error(
+            return Json(ApiResponse::<()>::error(
                 "Call router not available".to_string(),
-            )));
+            ))
+            .into_response();
         }
     };
 
-    let calls = call_router.get_active_calls().await;
-    let total = calls.len();
+    let sort = match page.sort(CALL_SORT_FIELDS, SortOrder::ascending("call_id")) {
+        Ok(sort) => sort,
+        Err(e) => return e.into_response(),
+    };
 
-    let response = ActiveCallsResponse { calls, total };
+    let mut calls = call_router.get_active_calls().await;
+    let total = calls.len() as i64;
+    calls.sort_by(|a, b| compare_calls(a, b, &sort));
+    let calls: Vec<ActiveCallInfo> = calls
+        .into_iter()
+        .skip(page.offset as usize)
+        .take(page.fetch_limit() as usize)
+        .collect();
 
-    Ok(Json(ApiResponse::success(response)))
+    // Counting is free here, but only reported when asked for
+    page.respond(calls, page.count.then_some(total))
 }
 
 /// Get active call by ID
@@ -94,7 +180,12 @@ pub async fn hangup_call(
         }
     };
 
-    match call_router.hangup_call(&call_id).await {
+    // Traced under the call's span, linked to this request's
+    let span = match &state.call_spans {
+        Some(call_spans) => call_spans.hangup_span(&call_id),
+        None => Span::none(),
+    };
+    match call_router.hangup_call(&call_id).instrument(span).await {
         Ok(_) => Ok(Json(ApiResponse::success(format!(
             "Call {} hung up successfully",
             call_id
@@ -109,6 +200,7 @@ pub async fn hangup_call(
 /// Get call statistics
 pub async fn get_call_stats(
     State(state): State<AppState>,
+    tz: TimeZoneContext,
 ) -> Result<Json<ApiResponse<CallStatsResponse>>, StatusCode> {
     info!("API: Getting call statistics");
 
@@ -135,12 +227,10 @@ pub async fn get_call_stats(
     // Get active calls count
     let total_active_calls = call_router.active_call_count().await;
 
-    // Get today's date range
-    let today_start = chrono::Utc::now()
-        .date_naive()
-        .and_hms_opt(0, 0, 0)
-        .unwrap()
-        .and_utc();
+    // Today starts at midnight in the requested time zone
+    let zone = tz.zone();
+    let today = chrono::Utc::now().with_timezone(&zone).date_naive();
+    let today_start = local_day_start(zone, today);
 
     // Build filter for today's calls
     let mut filters = crate::domain::cdr::CdrFilters::default();
@@ -200,6 +290,8 @@ pub async fn get_call_stats(
     };
 
     let stats = CallStatsResponse {
+        timezone: zone.name().to_string(),
+        today_start: in_zone(today_start, zone),
         total_active_calls,
         total_calls_today,
         total_completed_calls,
@@ -209,3 +301,174 @@ pub async fn get_call_stats(
 
     Ok(Json(ApiResponse::success(stats)))
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::domain::user::repository::MockUserRepository;
+    use crate::infrastructure::protocols::sip::{CallRouter, Registrar};
+    use axum::{body::Body, http::Request, routing::get, Router};
+    use serde_json::Value;
+    use std::sync::Arc;
+    use tower::ServiceExt;
+
+    #[tokio::test]
+    async fn test_active_calls_page_in_stable_order() {
+        let router = Arc::new(CallRouter::new(Arc::new(Registrar::new())));
+        for (call_id, callee) in [
+            ("call-c", "bob"),
+            ("call-a", "carol"),
+            ("call-e", "bob"),
+            ("call-b", "alice"),
+            ("call-d", "bob"),
+        ] {
+            router
+                .create_call(
+                    call_id.to_string(),
+                    "sip:alice@example.com".to_string(),
+                    format!("sip:{}@example.com", callee),
+                )
+                .await
+                .unwrap();
+        }
+        let mut state = AppState::for_tests(Arc::new(MockUserRepository::new()));
+        state.call_router = Some(router);
+        let app = Router::new()
+            .route("/calls", get(get_active_calls))
+            .with_state(state);
+
+        let mut listed = Vec::new();
+        let mut uri = "/calls?limit=2&sort=callee_uri&count=true".to_string();
+        loop {
+            let response = app
+                .clone()
+                .oneshot(Request::builder().uri(&uri).body(Body::empty()).unwrap())
+                .await
+                .unwrap();
+            assert_eq!(response.status(), StatusCode::OK);
+            let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+                .await
+                .unwrap();
+            let json: Value = serde_json::from_slice(&body).unwrap();
+            assert_eq!(json["data"]["total"], 5);
+            for call in json["data"]["items"].as_array().unwrap() {
+                listed.push(call["call_id"].as_str().unwrap().to_string());
+            }
+            match json["data"]["next_cursor"].as_str() {
+                Some(cursor) => {
+                    uri = format!("/calls?limit=2&sort=callee_uri&count=true&cursor={}", cursor)
+                }
+                None => break,
+            }
+        }
+
+        // Calls to the same callee stay in Call-ID order
+        assert_eq!(listed, ["call-b", "call-c", "call-d", "call-e", "call-a"]);
+
+        let response = app
+            .oneshot(
+                Request::builder()
+                    .uri("/calls?sort=state")
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    }
+
+    #[tokio::test]
+    async fn test_api_hangup_joins_caller_trace_and_call_span() {
+        use crate::application::call::CallApplicationService;
+        use crate::infrastructure::messaging::InProcessEventBus;
+        use crate::infrastructure::protocols::sip::SipMessage;
+        use crate::infrastructure::telemetry::{spawn_call_span_recorder, test_tracing, CallSpans};
+        use crate::interface::api::router::request_span;
+        use crate::test_support::SipRequestBuilder;
+        use axum::routing::post;
+        use opentelemetry::trace::{SpanId, TraceId};
+        use tower_http::trace::TraceLayer;
+
+        let (exporter, _provider, _guard) = test_tracing();
+        let bus = Arc::new(InProcessEventBus::default());
+        let router = Arc::new(
+            CallRouter::new(Arc::new(Registrar::new()))
+                .with_call_events(Arc::new(CallApplicationService::new(bus.clone()))),
+        );
+        let spans = Arc::new(CallSpans::new().with_call_router(router.clone()));
+        let recorder = spawn_call_span_recorder(bus.as_ref(), spans.clone());
+
+        let invite = SipRequestBuilder::invite("sip:alice@example.com", "sip:bob@example.com")
+            .build();
+        let call_id = invite.call_id().unwrap();
+        drop(spans.transaction_span(&SipMessage::Request(invite), None));
+        router
+            .create_call(
+                call_id.clone(),
+                "sip:alice@example.com".to_string(),
+                "sip:bob@example.com".to_string(),
+            )
+            .await
+            .unwrap();
+        router.answer_call(&call_id).await.unwrap();
+        spans.transaction_done(&call_id).await;
+
+        let mut state = AppState::for_tests(Arc::new(MockUserRepository::new()));
+        state.call_router = Some(router);
+        state.call_spans = Some(spans.clone());
+        let app = Router::new()
+            .route("/calls/:call_id/hangup", post(hangup_call))
+            .layer(TraceLayer::new_for_http().make_span_with(request_span))
+            .with_state(state);
+
+        let response = app
+            .oneshot(
+                Request::builder()
+                    .method("POST")
+                    .uri(format!("/calls/{}/hangup", call_id))
+                    .header(
+                        "traceparent",
+                        "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01",
+                    )
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        // The request's span ends with its response
+        drop(response);
+        // The call's span ends once the recorder sees the call end
+        tokio::time::timeout(std::time::Duration::from_secs(2), async {
+            while !spans.is_empty() {
+                tokio::time::sleep(std::time::Duration::from_millis(5)).await;
+            }
+        })
+        .await
+        .unwrap();
+        recorder.abort();
+
+        let finished = exporter.get_finished_spans().unwrap();
+        let find = |name: &str| finished.iter().find(|s| s.name == name).unwrap();
+        let (request, hangup, call) = (
+            find(&format!("POST /calls/{}/hangup", call_id)),
+            find("call.hangup"),
+            find("call"),
+        );
+
+        // The request continues the caller's trace; the hangup runs in it
+        let remote = TraceId::from_hex("4bf92f3577b34da6a3ce929d0e0e4736").unwrap();
+        assert_eq!(request.span_context.trace_id(), remote);
+        assert_eq!(request.parent_span_id, SpanId::from_hex("00f067aa0ba902b7").unwrap());
+        assert_eq!(hangup.parent_span_id, request.span_context.span_id());
+        assert!(hangup
+            .links
+            .links
+            .iter()
+            .any(|link| link.span_context.span_id() == call.span_context.span_id()));
+
+        let events: Vec<&str> = call.events.events.iter().map(|e| e.name.as_ref()).collect();
+        assert!(events.contains(&"hangup requested"));
+        assert_eq!(events.last(), Some(&"terminated"));
+    }
+}
@@ -0,0 +1,115 @@
+//! Campaign API handlers
+//!
+//! `POST /api/campaigns` creates a notification campaign that starts
+//! calling right away; `GET /api/campaigns/:id` reports its progress and the
+//! outcome of every target. `pause`, `resume` and `cancel` sub-resources
+//! control a running campaign.
+
+use super::cdr_dto::ApiResponse;
+use super::user_handler::AppState;
+use crate::application::campaign::CampaignService;
+use crate::domain::campaign::{CampaignError, CampaignSettings};
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    Json,
+};
+use std::sync::Arc;
+use tracing::{error, info, warn};
+use uuid::Uuid;
+
+#[allow(clippy::result_large_err)]
+fn service(state: &AppState) -> Result<&Arc<CampaignService>, Response> {
+    state.campaigns.as_ref().ok_or_else(|| {
+        error!("Campaign service not available");
+        (
+            StatusCode::SERVICE_UNAVAILABLE,
+            Json(ApiResponse::<()>::error("Campaigns not enabled".to_string())),
+        )
+            .into_response()
+    })
+}
+
+fn refused(e: CampaignError) -> Response {
+    let status = match e {
+        CampaignError::NotFound(_) => StatusCode::NOT_FOUND,
+        CampaignError::Invalid(_) => StatusCode::BAD_REQUEST,
+        CampaignError::InvalidState(_) => StatusCode::CONFLICT,
+    };
+    warn!("API: Campaign request refused: {}", e);
+    (status, Json(ApiResponse::<()>::error(e.to_string()))).into_response()
+}
+
+/// Create a campaign
+pub async fn create_campaign(
+    State(state): State<AppState>,
+    Json(settings): Json<CampaignSettings>,
+) -> Response {
+    let service = match service(&state) {
+        Ok(service) => service,
+        Err(response) => return response,
+    };
+    match service.create(settings) {
+        Ok(report) => {
+            info!("API: Created campaign {} ({})", report.id, report.name);
+            (StatusCode::CREATED, Json(ApiResponse::success(report))).into_response()
+        }
+        Err(e) => refused(e),
+    }
+}
+
+/// Every campaign, oldest first
+pub async fn list_campaigns(State(state): State<AppState>) -> Response {
+    match service(&state) {
+        Ok(service) => Json(ApiResponse::success(service.list())).into_response(),
+        Err(response) => response,
+    }
+}
+
+/// Progress and target outcomes of a campaign
+pub async fn get_campaign(State(state): State<AppState>, Path(id): Path<Uuid>) -> Response {
+    let service = match service(&state) {
+        Ok(service) => service,
+        Err(response) => return response,
+    };
+    match service.get(id) {
+        Ok(report) => Json(ApiResponse::success(report)).into_response(),
+        Err(e) => refused(e),
+    }
+}
+
+/// Stop placing calls; calls in progress go on
+pub async fn pause_campaign(State(state): State<AppState>, Path(id): Path<Uuid>) -> Response {
+    let service = match service(&state) {
+        Ok(service) => service,
+        Err(response) => return response,
+    };
+    match service.pause(id) {
+        Ok(report) => Json(ApiResponse::success(report)).into_response(),
+        Err(e) => refused(e),
+    }
+}
+
+pub async fn resume_campaign(State(state): State<AppState>, Path(id): Path<Uuid>) -> Response {
+    let service = match service(&state) {
+        Ok(service) => service,
+        Err(response) => return response,
+    };
+    match service.resume(id) {
+        Ok(report) => Json(ApiResponse::success(report)).into_response(),
+        Err(e) => refused(e),
+    }
+}
+
+/// End a campaign; targets not called yet are not called
+pub async fn cancel_campaign(State(state): State<AppState>, Path(id): Path<Uuid>) -> Response {
+    let service = match service(&state) {
+        Ok(service) => service,
+        Err(response) => return response,
+    };
+    match service.cancel(id) {
+        Ok(report) => Json(ApiResponse::success(report)).into_response(),
+        Err(e) => refused(e),
+    }
+}
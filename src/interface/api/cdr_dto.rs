@@ -1,11 +1,17 @@
 //! CDR API DTOs
 
+use super::openapi::{boolean, date_time, integer, nullable, object, string, uuid, ApiSchema};
 use crate::domain::cdr::CallDetailRecord;
-use chrono::{DateTime, Utc};
+use crate::domain::shared::time_zone::in_zone;
+use chrono::{DateTime, FixedOffset};
+use chrono_tz::Tz;
 use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
 use uuid::Uuid;
 
 /// CDR response
+///
+/// Times carry the offset of the requested time zone.
 #[derive(Debug, Serialize, Deserialize)]
 pub struct CdrResponse {
     pub id: Uuid,
@@ -17,12 +23,22 @@ pub struct CdrResponse {
     pub callee_uri: String,
     pub callee_ip: Option<String>,
     pub direction: String,
-    pub start_time: DateTime<Utc>,
-    pub answer_time: Option<DateTime<Utc>>,
-    pub end_time: Option<DateTime<Utc>>,
+    pub start_time: DateTime<FixedOffset>,
+    pub answer_time: Option<DateTime<FixedOffset>>,
+    pub end_time: Option<DateTime<FixedOffset>>,
+    pub early_media_time: Option<DateTime<FixedOffset>>,
+    pub ack_time: Option<DateTime<FixedOffset>>,
+    pub ring_time: Option<DateTime<FixedOffset>>,
     pub setup_duration: Option<i32>,
+    pub ring_duration: Option<i32>,
     pub call_duration: Option<i32>,
+    pub talk_duration: Option<i32>,
     pub total_duration: Option<i32>,
+    /// Milliseconds from the INVITE to each setup milestone
+    pub auth_latency_ms: Option<i32>,
+    pub routing_latency_ms: Option<i32>,
+    pub provisional_latency_ms: Option<i32>,
+    pub answer_latency_ms: Option<i32>,
     pub status: String,
     pub end_reason: Option<String>,
     pub sip_response_code: Option<u16>,
@@ -31,12 +47,84 @@ pub struct CdrResponse {
     pub rtp_packets_received: Option<i64>,
     pub rtp_bytes_sent: Option<i64>,
     pub rtp_bytes_received: Option<i64>,
-    pub created_at: DateTime<Utc>,
-    pub updated_at: DateTime<Utc>,
+    pub correlation_id: Option<String>,
+    pub dialed_number: Option<String>,
+    pub redirect_count: i32,
+    pub hold_duration: i32,
+    pub hold_count: i32,
+    pub test_call: bool,
+    pub announcement_variant: Option<String>,
+    pub trunk: Option<String>,
+    pub suspect_answer: bool,
+    pub live_transcribed: bool,
+    pub codec_fallback: Option<String>,
+    pub created_at: DateTime<FixedOffset>,
+    pub updated_at: DateTime<FixedOffset>,
+}
+
+impl ApiSchema for CdrResponse {
+    const NAME: &'static str = "Cdr";
+
+    fn schema() -> Value {
+        object(json!({
+            "id": uuid(),
+            "call_id": string(),
+            "caller_username": string(),
+            "caller_uri": string(),
+            "caller_ip": string(),
+            "callee_username": string(),
+            "callee_uri": string(),
+            "callee_ip": nullable(string()),
+            "direction": string(),
+            "start_time": date_time(),
+            "answer_time": nullable(date_time()),
+            "end_time": nullable(date_time()),
+            "early_media_time": nullable(date_time()),
+            "ack_time": nullable(date_time()),
+            "ring_time": nullable(date_time()),
+            "setup_duration": nullable(integer()),
+            "ring_duration": nullable(integer()),
+            "call_duration": nullable(integer()),
+            "talk_duration": nullable(integer()),
+            "total_duration": nullable(integer()),
+            "auth_latency_ms": nullable(integer()),
+            "routing_latency_ms": nullable(integer()),
+            "provisional_latency_ms": nullable(integer()),
+            "answer_latency_ms": nullable(integer()),
+            "status": string(),
+            "end_reason": nullable(string()),
+            "sip_response_code": nullable(integer()),
+            "codec": nullable(string()),
+            "rtp_packets_sent": nullable(integer()),
+            "rtp_packets_received": nullable(integer()),
+            "rtp_bytes_sent": nullable(integer()),
+            "rtp_bytes_received": nullable(integer()),
+            "correlation_id": nullable(string()),
+            "dialed_number": nullable(string()),
+            "redirect_count": integer(),
+            "hold_duration": integer(),
+            "hold_count": integer(),
+            "test_call": boolean(),
+            "announcement_variant": nullable(string()),
+            "trunk": nullable(string()),
+            "suspect_answer": boolean(),
+            "live_transcribed": boolean(),
+            "codec_fallback": nullable(string()),
+            "created_at": date_time(),
+            "updated_at": date_time(),
+        }))
+    }
 }
 
 impl From<CallDetailRecord> for CdrResponse {
     fn from(cdr: CallDetailRecord) -> Self {
+        Self::in_zone(cdr, Tz::UTC)
+    }
+}
+
+impl CdrResponse {
+    /// `cdr` with its times in `zone`
+    pub fn in_zone(cdr: CallDetailRecord, zone: Tz) -> Self {
         CdrResponse {
             id: cdr.id,
             call_id: cdr.call_id,
@@ -47,12 +135,21 @@ impl From<CallDetailRecord> for CdrResponse {
             callee_uri: cdr.callee_uri,
             callee_ip: cdr.callee_ip,
             direction: cdr.direction.as_str().to_string(),
-            start_time: cdr.start_time,
-            answer_time: cdr.answer_time,
-            end_time: cdr.end_time,
+            start_time: in_zone(cdr.start_time, zone),
+            answer_time: cdr.answer_time.map(|time| in_zone(time, zone)),
+            end_time: cdr.end_time.map(|time| in_zone(time, zone)),
+            early_media_time: cdr.early_media_time.map(|time| in_zone(time, zone)),
+            ack_time: cdr.ack_time.map(|time| in_zone(time, zone)),
+            ring_time: cdr.ring_time.map(|time| in_zone(time, zone)),
             setup_duration: cdr.setup_duration,
+            ring_duration: cdr.ring_duration,
             call_duration: cdr.call_duration,
+            talk_duration: cdr.talk_duration,
             total_duration: cdr.total_duration,
+            auth_latency_ms: cdr.auth_latency_ms,
+            routing_latency_ms: cdr.routing_latency_ms,
+            provisional_latency_ms: cdr.provisional_latency_ms,
+            answer_latency_ms: cdr.answer_latency_ms,
             status: cdr.status.as_str().to_string(),
             end_reason: cdr.end_reason,
             sip_response_code: cdr.sip_response_code,
@@ -61,21 +158,23 @@ impl From<CallDetailRecord> for CdrResponse {
             rtp_packets_received: cdr.rtp_packets_received,
             rtp_bytes_sent: cdr.rtp_bytes_sent,
             rtp_bytes_received: cdr.rtp_bytes_received,
-            created_at: cdr.created_at,
-            updated_at: cdr.updated_at,
+            correlation_id: cdr.correlation_id,
+            dialed_number: cdr.dialed_number,
+            redirect_count: cdr.redirect_count,
+            hold_duration: cdr.hold_duration,
+            hold_count: cdr.hold_count,
+            test_call: cdr.test_call,
+            announcement_variant: cdr.announcement_variant,
+            trunk: cdr.trunk,
+            suspect_answer: cdr.suspect_answer,
+            live_transcribed: cdr.live_transcribed,
+            codec_fallback: cdr.codec_fallback,
+            created_at: in_zone(cdr.created_at, zone),
+            updated_at: in_zone(cdr.updated_at, zone),
         }
     }
 }
 
-/// CDR list response
-#[derive(Debug, Serialize, Deserialize)]
-pub struct CdrListResponse {
-    pub cdrs: Vec<CdrResponse>,
-    pub total: i64,
-    pub limit: i64,
-    pub offset: i64,
-}
-
 /// Generic API response wrapper
 #[derive(Debug, Serialize, Deserialize)]
 pub struct ApiResponse<T> {
@@ -1,43 +1,125 @@
 //! CDR API handlers
 
-use super::cdr_dto::{ApiResponse, CdrListResponse, CdrResponse};
+use super::cdr_dto::{ApiResponse, CdrResponse};
+use super::pagination::Pagination;
+use super::timezone::{zone_header, TimeZoneContext, TimeZoneError};
 use super::user_handler::AppState;
 use crate::domain::cdr::{CdrFilters, CallDirection, CallStatus};
+use crate::domain::shared::time_zone::{day_buckets, in_zone, local_day_start};
+use crate::domain::shared::SortOrder;
 use axum::{
     extract::{Path, Query, State},
     http::{header, StatusCode},
     response::{IntoResponse, Response},
     Json,
 };
-use chrono::{DateTime, Utc};
-use serde::Deserialize;
+use chrono::{DateTime, Duration, FixedOffset, NaiveDate, Utc};
+use chrono_tz::Tz;
+use serde::{Deserialize, Serialize};
 use tracing::{error, info};
 use uuid::Uuid;
 
-/// Query parameters for listing CDRs
+/// Query parameters for listing CDRs, besides [`Pagination`] and `tz`
 #[derive(Debug, Deserialize)]
 pub struct ListCdrsQuery {
-    #[serde(default = "default_limit")]
-    pub limit: i64,
-    #[serde(default)]
-    pub offset: i64,
     pub caller_username: Option<String>,
     pub callee_username: Option<String>,
     pub direction: Option<String>,
     pub status: Option<String>,
-    pub start_time_from: Option<DateTime<Utc>>,
-    pub start_time_to: Option<DateTime<Utc>>,
+    /// RFC 3339 time, or a date meaning its midnight in the time zone
+    pub start_time_from: Option<String>,
+    /// RFC 3339 time (exclusive), or a date including the whole day
+    pub start_time_to: Option<String>,
     pub min_duration: Option<i32>,
 }
 
-fn default_limit() -> i64 {
-    100
+impl ListCdrsQuery {
+    /// Filters of the query, with dates read in `zone`
+    fn filters(self, tz: &TimeZoneContext, zone: Tz) -> Result<CdrFilters, TimeZoneError> {
+        let mut filters = CdrFilters {
+            start_time_from: tz.bound(
+                zone,
+                "start_time_from",
+                self.start_time_from.as_deref(),
+                false,
+            )?,
+            start_time_to: tz.bound(zone, "start_time_to", self.start_time_to.as_deref(), true)?,
+            caller_username: self.caller_username,
+            callee_username: self.callee_username,
+            min_duration: self.min_duration,
+            ..Default::default()
+        };
+
+        // Parse direction
+        if let Some(ref dir_str) = self.direction {
+            filters.direction = match dir_str.as_str() {
+                "inbound" => Some(CallDirection::Inbound),
+                "outbound" => Some(CallDirection::Outbound),
+                "internal" => Some(CallDirection::Internal),
+                _ => None,
+            };
+        }
+
+        // Parse status
+        if let Some(ref status_str) = self.status {
+            filters.status = CallStatus::from_str(status_str);
+        }
+
+        Ok(filters)
+    }
+}
+
+/// Query parameters of CDR statistics, besides `tz`
+#[derive(Debug, Deserialize)]
+pub struct CdrStatsQuery {
+    /// RFC 3339 time, or a date meaning its midnight in the time zone;
+    /// the start of the day `DEFAULT_STATS_DAYS` ago when absent
+    pub from: Option<String>,
+    /// RFC 3339 time (exclusive), or a date including the whole day; now
+    /// when absent
+    pub to: Option<String>,
 }
 
+/// Days reported when `from` is absent
+const DEFAULT_STATS_DAYS: i64 = 7;
+
+/// Most days a statistics request may span
+const MAX_STATS_DAYS: usize = 366;
+
+/// Calls of one local day
+#[derive(Debug, Serialize, Deserialize)]
+pub struct CdrDayStats {
+    /// Calendar day in the time zone
+    pub date: NaiveDate,
+    pub start: DateTime<FixedOffset>,
+    pub end: DateTime<FixedOffset>,
+    pub total_calls: i64,
+    pub completed_calls: i64,
+}
+
+/// CDR statistics per local day
+#[derive(Debug, Serialize, Deserialize)]
+pub struct CdrStatsResponse {
+    /// Time zone of the days and timestamps
+    pub timezone: String,
+    pub from: DateTime<FixedOffset>,
+    pub to: DateTime<FixedOffset>,
+    pub days: Vec<CdrDayStats>,
+}
+
+/// Fields CDRs can be sorted by
+const CDR_SORT_FIELDS: &[&str] = &[
+    "start_time",
+    "call_duration",
+    "caller_username",
+    "callee_username",
+];
+
 /// Get CDR by ID
 pub async fn get_cdr(
     State(state): State<AppState>,
     Path(id): Path<Uuid>,
+    tz: TimeZoneContext,
 ) -> Result<Json<ApiResponse<CdrResponse>>, StatusCode> {
     info!("API: Getting CDR ID: {}", id);
 
@@ -50,7 +132,7 @@ pub async fn get_cdr(
     };
 
     match cdr_repo.get_by_id(id).await {
-        Ok(Some(cdr)) => Ok(Json(ApiResponse::success(cdr.into()))),
+        Ok(Some(cdr)) => Ok(Json(ApiResponse::success(CdrResponse::in_zone(cdr, tz.zone())))),
         Ok(None) => Ok(Json(ApiResponse::error(format!("CDR {} not found", id)))),
         Err(e) => {
             error!("API: Failed to get CDR: {}", e);
@@ -63,6 +145,7 @@ pub async fn get_cdr(
 pub async fn get_cdr_by_call_id(
     State(state): State<AppState>,
     Path(call_id): Path<String>,
+    tz: TimeZoneContext,
 ) -> Result<Json<ApiResponse<CdrResponse>>, StatusCode> {
     info!("API: Getting CDR by Call-ID: {}", call_id);
 
@@ -75,7 +158,7 @@ pub async fn get_cdr_by_call_id(
     };
 
     match cdr_repo.get_by_call_id(&call_id).await {
-        Ok(Some(cdr)) => Ok(Json(ApiResponse::success(cdr.into()))),
+        Ok(Some(cdr)) => Ok(Json(ApiResponse::success(CdrResponse::in_zone(cdr, tz.zone())))),
         Ok(None) => Ok(Json(ApiResponse::error(format!(
             "CDR for Call-ID {} not found",
             call_id
@@ -90,63 +173,58 @@ pub async fn get_cdr_by_call_id(
 /// List CDRs
 pub async fn list_cdrs(
     State(state): State<AppState>,
+    page: Pagination,
+    tz: TimeZoneContext,
     Query(query): Query<ListCdrsQuery>,
-) -> Result<Json<ApiResponse<CdrListResponse>>, StatusCode> {
+) -> Response {
     info!(
         "API: Listing CDRs (limit: {}, offset: {})",
-        query.limit, query.offset
+        page.limit, page.offset
     );
 
     let cdr_repo = match &state.cdr_repository {
         Some(repo) => repo,
         None => {
             error!("CDR repository not available");
-            return Ok(Json(ApiResponse::error("CDR repository not available".to_string())));
+            return Json(ApiResponse::<()>::error("CDR repository not available".to_string()))
+                .into_response();
         }
     };
 
-    // Build filters
-    let mut filters = CdrFilters::default();
-    filters.caller_username = query.caller_username;
-    filters.callee_username = query.callee_username;
-    filters.start_time_from = query.start_time_from;
-    filters.start_time_to = query.start_time_to;
-    filters.min_duration = query.min_duration;
-
-    // Parse direction
-    if let Some(ref dir_str) = query.direction {
-        filters.direction = match dir_str.as_str() {
-            "inbound" => Some(CallDirection::Inbound),
-            "outbound" => Some(CallDirection::Outbound),
-            "internal" => Some(CallDirection::Internal),
-            _ => None,
-        };
-    }
+    let zone = tz.zone();
+    let mut filters = match query.filters(&tz, zone) {
+        Ok(filters) => filters,
+        Err(e) => return e.into_response(),
+    };
 
-    // Parse status
-    if let Some(ref status_str) = query.status {
-        filters.status = CallStatus::from_str(status_str);
-    }
+    filters.sort = match page.sort(CDR_SORT_FIELDS, SortOrder::descending("start_time")) {
+        Ok(sort) => Some(sort),
+        Err(e) => return e.into_response(),
+    };
 
     // Get CDRs
-    let cdrs_result = cdr_repo.list(filters.clone(), query.limit, query.offset).await;
+    let cdrs_result = cdr_repo
+        .list(filters.clone(), page.fetch_limit(), page.offset)
+        .await;
 
-    // Get total count
-    let count_result = cdr_repo.count(filters).await;
+    // Get total count, only when asked for
+    let count_result = if page.count {
+        cdr_repo.count(filters).await.map(Some)
+    } else {
+        Ok(None)
+    };
 
     match (cdrs_result, count_result) {
         (Ok(cdrs), Ok(total)) => {
-            let response = CdrListResponse {
-                cdrs: cdrs.into_iter().map(|c| c.into()).collect(),
-                total,
-                limit: query.limit,
-                offset: query.offset,
-            };
-            Ok(Json(ApiResponse::success(response)))
+            let cdrs: Vec<CdrResponse> = cdrs
+                .into_iter()
+                .map(|cdr| CdrResponse::in_zone(cdr, zone))
+                .collect();
+            (zone_header(zone), page.respond(cdrs, total)).into_response()
         }
         (Err(e), _) | (_, Err(e)) => {
             error!("API: Failed to list CDRs: {}", e);
-            Ok(Json(ApiResponse::error(e.to_string())))
+            Json(ApiResponse::<()>::error(e.to_string())).into_response()
         }
     }
 }
@@ -154,6 +232,7 @@ pub async fn list_cdrs(
 /// Export CDRs as CSV
 pub async fn export_cdrs_csv(
     State(state): State<AppState>,
+    tz: TimeZoneContext,
     Query(query): Query<ListCdrsQuery>,
 ) -> Result<Response, StatusCode> {
     info!("API: Exporting CDRs as CSV");
@@ -166,28 +245,11 @@ pub async fn export_cdrs_csv(
         }
     };
 
-    // Build filters
-    let mut filters = CdrFilters::default();
-    filters.caller_username = query.caller_username;
-    filters.callee_username = query.callee_username;
-    filters.start_time_from = query.start_time_from;
-    filters.start_time_to = query.start_time_to;
-    filters.min_duration = query.min_duration;
-
-    // Parse direction
-    if let Some(ref dir_str) = query.direction {
-        filters.direction = match dir_str.as_str() {
-            "inbound" => Some(CallDirection::Inbound),
-            "outbound" => Some(CallDirection::Outbound),
-            "internal" => Some(CallDirection::Internal),
-            _ => None,
-        };
-    }
-
-    // Parse status
-    if let Some(ref status_str) = query.status {
-        filters.status = CallStatus::from_str(status_str);
-    }
+    let zone = tz.zone();
+    let filters = match query.filters(&tz, zone) {
+        Ok(filters) => filters,
+        Err(e) => return Ok(e.into_response()),
+    };
 
     // Get CDRs (export all matching records, not paginated)
     let cdrs = match cdr_repo.list(filters, 10000, 0).await {
@@ -217,9 +279,9 @@ pub async fn export_cdrs_csv(
             escape_csv(&cdr.callee_uri),
             cdr.callee_ip.as_ref().map(|s| escape_csv(s)).unwrap_or_default(),
             cdr.direction.as_str(),
-            cdr.start_time.to_rfc3339(),
-            cdr.answer_time.as_ref().map(|t| t.to_rfc3339()).unwrap_or_default(),
-            cdr.end_time.as_ref().map(|t| t.to_rfc3339()).unwrap_or_default(),
+            in_zone(cdr.start_time, zone).to_rfc3339(),
+            cdr.answer_time.map(|t| in_zone(t, zone).to_rfc3339()).unwrap_or_default(),
+            cdr.end_time.map(|t| in_zone(t, zone).to_rfc3339()).unwrap_or_default(),
             cdr.setup_duration.map(|d| d.to_string()).unwrap_or_default(),
             cdr.call_duration.map(|d| d.to_string()).unwrap_or_default(),
             cdr.total_duration.map(|d| d.to_string()).unwrap_or_default(),
@@ -231,8 +293,8 @@ pub async fn export_cdrs_csv(
             cdr.rtp_packets_received.map(|p| p.to_string()).unwrap_or_default(),
             cdr.rtp_bytes_sent.map(|b| b.to_string()).unwrap_or_default(),
             cdr.rtp_bytes_received.map(|b| b.to_string()).unwrap_or_default(),
-            cdr.created_at.to_rfc3339(),
-            cdr.updated_at.to_rfc3339(),
+            in_zone(cdr.created_at, zone).to_rfc3339(),
+            in_zone(cdr.updated_at, zone).to_rfc3339(),
         ));
     }
 
@@ -242,6 +304,7 @@ pub async fn export_cdrs_csv(
             (header::CONTENT_TYPE, "text/csv"),
             (header::CONTENT_DISPOSITION, "attachment; filename=\"cdrs.csv\""),
         ],
+        zone_header(zone),
         csv_content,
     ).into_response())
 }
@@ -249,6 +312,7 @@ pub async fn export_cdrs_csv(
 /// Export CDRs as JSON
 pub async fn export_cdrs_json(
     State(state): State<AppState>,
+    tz: TimeZoneContext,
     Query(query): Query<ListCdrsQuery>,
 ) -> Result<Response, StatusCode> {
     info!("API: Exporting CDRs as JSON");
@@ -261,28 +325,11 @@ pub async fn export_cdrs_json(
         }
     };
 
-    // Build filters
-    let mut filters = CdrFilters::default();
-    filters.caller_username = query.caller_username;
-    filters.callee_username = query.callee_username;
-    filters.start_time_from = query.start_time_from;
-    filters.start_time_to = query.start_time_to;
-    filters.min_duration = query.min_duration;
-
-    // Parse direction
-    if let Some(ref dir_str) = query.direction {
-        filters.direction = match dir_str.as_str() {
-            "inbound" => Some(CallDirection::Inbound),
-            "outbound" => Some(CallDirection::Outbound),
-            "internal" => Some(CallDirection::Internal),
-            _ => None,
-        };
-    }
-
-    // Parse status
-    if let Some(ref status_str) = query.status {
-        filters.status = CallStatus::from_str(status_str);
-    }
+    let zone = tz.zone();
+    let filters = match query.filters(&tz, zone) {
+        Ok(filters) => filters,
+        Err(e) => return Ok(e.into_response()),
+    };
 
     // Get CDRs (export all matching records, not paginated)
     let cdrs = match cdr_repo.list(filters, 10000, 0).await {
@@ -294,7 +341,10 @@ pub async fn export_cdrs_json(
     };
 
     // Convert to response DTOs
-    let cdr_responses: Vec<CdrResponse> = cdrs.into_iter().map(|c| c.into()).collect();
+    let cdr_responses: Vec<CdrResponse> = cdrs
+        .into_iter()
+        .map(|cdr| CdrResponse::in_zone(cdr, zone))
+        .collect();
 
     // Serialize to JSON
     let json_content = match serde_json::to_string_pretty(&cdr_responses) {
@@ -311,10 +361,209 @@ pub async fn export_cdrs_json(
             (header::CONTENT_TYPE, "application/json"),
             (header::CONTENT_DISPOSITION, "attachment; filename=\"cdrs.json\""),
         ],
+        zone_header(zone),
         json_content,
     ).into_response())
 }
 
+/// Get CDR statistics per day of the time zone
+pub async fn get_cdr_stats(
+    State(state): State<AppState>,
+    tz: TimeZoneContext,
+    Query(query): Query<CdrStatsQuery>,
+) -> Response {
+    let zone = tz.zone();
+    info!("API: Getting CDR statistics in {}", zone);
+
+    let cdr_repo = match &state.cdr_repository {
+        Some(repo) => repo,
+        None => {
+            error!("CDR repository not available");
+            return Json(ApiResponse::<()>::error("CDR repository not available".to_string()))
+                .into_response();
+        }
+    };
+
+    let (from, to) = match (
+        tz.bound(zone, "from", query.from.as_deref(), false),
+        tz.bound(zone, "to", query.to.as_deref(), true),
+    ) {
+        (Ok(from), Ok(to)) => {
+            let to = to.unwrap_or_else(Utc::now);
+            let from = from.unwrap_or_else(|| {
+                let last_day = to.with_timezone(&zone).date_naive();
+                local_day_start(zone, last_day - Duration::days(DEFAULT_STATS_DAYS - 1))
+            });
+            (from, to)
+        }
+        (Err(e), _) | (_, Err(e)) => return e.into_response(),
+    };
+    if from >= to {
+        return TimeZoneError("from must be before to".to_string()).into_response();
+    }
+
+    let buckets = day_buckets(zone, from, to);
+    if buckets.len() > MAX_STATS_DAYS {
+        return TimeZoneError(format!(
+            "At most {} days can be reported, asked for {}",
+            MAX_STATS_DAYS,
+            buckets.len()
+        ))
+        .into_response();
+    }
+
+    let mut days = Vec::with_capacity(buckets.len());
+    for bucket in buckets {
+        let mut filters = CdrFilters {
+            start_time_from: Some(bucket.start),
+            start_time_to: Some(bucket.end),
+            ..Default::default()
+        };
+        let total_calls = cdr_repo.count(filters.clone()).await;
+        filters.status = Some(CallStatus::Completed);
+        let completed_calls = cdr_repo.count(filters).await;
+        match (total_calls, completed_calls) {
+            (Ok(total_calls), Ok(completed_calls)) => days.push(CdrDayStats {
+                date: bucket.date,
+                start: in_zone(bucket.start, zone),
+                end: in_zone(bucket.end, zone),
+                total_calls,
+                completed_calls,
+            }),
+            (Err(e), _) | (_, Err(e)) => {
+                error!("API: Failed to count CDRs: {}", e);
+                return Json(ApiResponse::<()>::error(e)).into_response();
+            }
+        }
+    }
+
+    (
+        zone_header(zone),
+        Json(ApiResponse::success(CdrStatsResponse {
+            timezone: zone.name().to_string(),
+            from: in_zone(from, zone),
+            to: in_zone(to, zone),
+            days,
+        })),
+    )
+        .into_response()
+}
+
+/// Query parameters of the suspect call report, besides `tz`
+#[derive(Debug, Deserialize)]
+pub struct SuspectCallsQuery {
+    /// RFC 3339 time, or a date meaning its midnight in the time zone
+    pub from: Option<String>,
+    /// RFC 3339 time (exclusive), or a date including the whole day
+    pub to: Option<String>,
+    /// Answered calls up to this many seconds are reported; defaults to
+    /// `answer_supervision.report_threshold_secs`
+    pub max_duration: Option<i32>,
+    pub trunk: Option<String>,
+}
+
+/// Most CDRs a suspect call report looks at
+const MAX_SUSPECT_SCAN: i64 = 10_000;
+
+/// Answered calls that may have been answered by the far end's equipment
+#[derive(Debug, Serialize, Deserialize)]
+pub struct SuspectCallsResponse {
+    /// Time zone of the timestamps
+    pub timezone: String,
+    pub from: DateTime<FixedOffset>,
+    pub to: DateTime<FixedOffset>,
+    pub max_duration: i32,
+    pub calls: Vec<CdrResponse>,
+    pub total: usize,
+    /// More CDRs started in the window than were looked at
+    pub truncated: bool,
+}
+
+/// Report answered calls shorter than a threshold, and calls flagged as
+/// answered too early after their ACK, for disputes with carriers
+pub async fn get_suspect_calls(
+    State(state): State<AppState>,
+    tz: TimeZoneContext,
+    Query(query): Query<SuspectCallsQuery>,
+) -> Response {
+    let zone = tz.zone();
+    info!("API: Reporting suspect answered calls in {}", zone);
+
+    let cdr_repo = match &state.cdr_repository {
+        Some(repo) => repo,
+        None => {
+            error!("CDR repository not available");
+            return Json(ApiResponse::<()>::error("CDR repository not available".to_string()))
+                .into_response();
+        }
+    };
+
+    let (from, to) = match (
+        tz.bound(zone, "from", query.from.as_deref(), false),
+        tz.bound(zone, "to", query.to.as_deref(), true),
+    ) {
+        (Ok(from), Ok(to)) => {
+            let to = to.unwrap_or_else(Utc::now);
+            let from = from.unwrap_or_else(|| {
+                let last_day = to.with_timezone(&zone).date_naive();
+                local_day_start(zone, last_day - Duration::days(DEFAULT_STATS_DAYS - 1))
+            });
+            (from, to)
+        }
+        (Err(e), _) | (_, Err(e)) => return e.into_response(),
+    };
+    if from >= to {
+        return TimeZoneError("from must be before to".to_string()).into_response();
+    }
+    let max_duration = query
+        .max_duration
+        .unwrap_or(state.answer_supervision.report_threshold_secs as i32);
+
+    let filters = CdrFilters {
+        start_time_from: Some(from),
+        start_time_to: Some(to),
+        ..Default::default()
+    };
+    let cdrs = match cdr_repo.list(filters, MAX_SUSPECT_SCAN + 1, 0).await {
+        Ok(cdrs) => cdrs,
+        Err(e) => {
+            error!("API: Failed to list CDRs: {}", e);
+            return Json(ApiResponse::<()>::error(e)).into_response();
+        }
+    };
+    let truncated = cdrs.len() as i64 > MAX_SUSPECT_SCAN;
+
+    let calls: Vec<CdrResponse> = cdrs
+        .into_iter()
+        .take(MAX_SUSPECT_SCAN as usize)
+        .filter(|cdr| cdr.answer_time.is_some())
+        .filter(|cdr| {
+            cdr.suspect_answer || cdr.call_duration.is_some_and(|secs| secs <= max_duration)
+        })
+        .filter(|cdr| {
+            query
+                .trunk
+                .as_deref()
+                .is_none_or(|trunk| cdr.trunk.as_deref() == Some(trunk))
+        })
+        .map(|cdr| CdrResponse::in_zone(cdr, zone))
+        .collect();
+
+    (
+        zone_header(zone),
+        Json(ApiResponse::success(SuspectCallsResponse {
+            timezone: zone.name().to_string(),
+            from: in_zone(from, zone),
+            to: in_zone(to, zone),
+            max_duration,
+            total: calls.len(),
+            calls,
+            truncated,
+        })),
+    )
+        .into_response()
+}
+
 /// Escape CSV field
 fn escape_csv(s: &str) -> String {
     if s.contains(',') || s.contains('"') || s.contains('\n') {
@@ -323,3 +572,199 @@ fn escape_csv(s: &str) -> String {
         s.to_string()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::domain::cdr::{CallDetailRecord, MockCdrRepository};
+    use crate::domain::user::repository::MockUserRepository;
+    use axum::{body::Body, http::Request, routing::get, Router};
+    use serde_json::Value;
+    use std::sync::Arc;
+    use tower::ServiceExt;
+
+    #[tokio::test]
+    async fn test_list_cdrs_pages_in_stable_order() {
+        // Equal durations: the id tiebreaker keeps their order across pages
+        let mut cdrs: Vec<CallDetailRecord> = [30, 90, 30, 60, 30]
+            .iter()
+            .enumerate()
+            .map(|(i, duration)| {
+                let mut cdr = CallDetailRecord::new(
+                    format!("call-{}", i),
+                    "alice".to_string(),
+                    "sip:alice@example.com".to_string(),
+                    "192.168.1.100".to_string(),
+                    "bob".to_string(),
+                    "sip:bob@example.com".to_string(),
+                    CallDirection::Internal,
+                );
+                cdr.call_duration = Some(*duration);
+                cdr
+            })
+            .collect();
+        cdrs.sort_by(|a, b| b.call_duration.cmp(&a.call_duration).then(b.id.cmp(&a.id)));
+        let expected: Vec<String> = cdrs.iter().map(|c| c.call_id.clone()).collect();
+
+        let mut repository = MockCdrRepository::new();
+        repository.expect_list().returning(move |filters, limit, offset| {
+            assert_eq!(filters.sort, Some(SortOrder::descending("call_duration")));
+            Ok(cdrs
+                .iter()
+                .skip(offset as usize)
+                .take(limit as usize)
+                .cloned()
+                .collect())
+        });
+        repository.expect_count().never();
+        let mut state = AppState::for_tests(Arc::new(MockUserRepository::new()));
+        state.cdr_repository = Some(Arc::new(repository));
+        let app = Router::new()
+            .route("/cdrs", get(list_cdrs))
+            .with_state(state);
+
+        let mut listed = Vec::new();
+        let mut uri = Some("/cdrs?limit=2&sort=-call_duration&fields=call_id".to_string());
+        while let Some(next) = uri.take() {
+            let response = app
+                .clone()
+                .oneshot(Request::builder().uri(next).body(Body::empty()).unwrap())
+                .await
+                .unwrap();
+            assert_eq!(response.status(), StatusCode::OK);
+            // Follow the Link header to the next page
+            uri = response
+                .headers()
+                .get(header::LINK)
+                .and_then(|links| {
+                    links
+                        .to_str()
+                        .unwrap()
+                        .split(", ")
+                        .find(|link| link.ends_with("rel=\"next\""))
+                        .map(|link| link[1..link.find('>').unwrap()].to_string())
+                });
+            let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+                .await
+                .unwrap();
+            let json: Value = serde_json::from_slice(&body).unwrap();
+            assert!(json["data"].get("total").is_none());
+            for cdr in json["data"]["items"].as_array().unwrap() {
+                // Only the selected field is returned
+                assert_eq!(cdr.as_object().unwrap().len(), 1);
+                listed.push(cdr["call_id"].as_str().unwrap().to_string());
+            }
+        }
+
+        assert_eq!(listed, expected);
+    }
+
+    #[tokio::test]
+    async fn test_stats_bucket_local_days_across_dst_end() {
+        // Berlin leaves summer time on 2024-10-27: that day is 25 hours long
+        let cdrs: Vec<CallDetailRecord> = [
+            ((2024, 10, 26, 21, 30), CallStatus::Completed), // 26th, 23:30 CEST
+            ((2024, 10, 26, 22, 30), CallStatus::Completed), // 27th, 00:30 CEST
+            ((2024, 10, 27, 22, 30), CallStatus::Failed),    // 27th, 23:30 CET
+        ]
+        .into_iter()
+        .enumerate()
+        .map(|(i, ((y, mo, d, h, mi), status))| {
+            let mut cdr = CallDetailRecord::new(
+                format!("call-{}", i),
+                "alice".to_string(),
+                "sip:alice@example.com".to_string(),
+                "192.168.1.100".to_string(),
+                "bob".to_string(),
+                "sip:bob@example.com".to_string(),
+                CallDirection::Internal,
+            );
+            cdr.start_time = chrono::TimeZone::with_ymd_and_hms(&Utc, y, mo, d, h, mi, 0).unwrap();
+            cdr.status = status;
+            cdr
+        })
+        .collect();
+
+        let mut repository = MockCdrRepository::new();
+        repository.expect_count().returning(move |filters| {
+            Ok(cdrs
+                .iter()
+                .filter(|cdr| filters.start_time_from.is_none_or(|from| cdr.start_time >= from))
+                .filter(|cdr| filters.start_time_to.is_none_or(|to| cdr.start_time < to))
+                .filter(|cdr| filters.status.is_none_or(|status| cdr.status == status))
+                .count() as i64)
+        });
+        let mut state = AppState::for_tests(Arc::new(MockUserRepository::new()));
+        state.cdr_repository = Some(Arc::new(repository));
+        let app = Router::new()
+            .route("/cdrs/stats", get(get_cdr_stats))
+            .with_state(state);
+
+        let response = app
+            .oneshot(
+                Request::builder()
+                    .uri("/cdrs/stats?from=2024-10-26&to=2024-10-27&tz=Europe/Berlin")
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let json: Value = serde_json::from_slice(&body).unwrap();
+        let stats: CdrStatsResponse = serde_json::from_value(json["data"].clone()).unwrap();
+
+        assert_eq!(stats.timezone, "Europe/Berlin");
+        let days: Vec<(String, String, String, i64, i64)> = stats
+            .days
+            .iter()
+            .map(|day| {
+                (
+                    day.date.to_string(),
+                    day.start.to_rfc3339(),
+                    day.end.to_rfc3339(),
+                    day.total_calls,
+                    day.completed_calls,
+                )
+            })
+            .collect();
+        assert_eq!(
+            days,
+            vec![
+                (
+                    "2024-10-26".to_string(),
+                    "2024-10-26T00:00:00+02:00".to_string(),
+                    "2024-10-27T00:00:00+02:00".to_string(),
+                    1,
+                    1
+                ),
+                (
+                    "2024-10-27".to_string(),
+                    "2024-10-27T00:00:00+02:00".to_string(),
+                    "2024-10-28T00:00:00+01:00".to_string(),
+                    2,
+                    1
+                ),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_unknown_time_zone_is_rejected() {
+        let app = Router::new()
+            .route("/cdrs/stats", get(get_cdr_stats))
+            .with_state(AppState::for_tests(Arc::new(MockUserRepository::new())));
+        let response = app
+            .oneshot(
+                Request::builder()
+                    .uri("/cdrs/stats?tz=Mars/Olympus")
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    }
+}
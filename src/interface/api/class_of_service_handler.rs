@@ -0,0 +1,206 @@
+//! Class of service API handlers
+//!
+//! `GET /api/classes-of-service` lists the classes and destination
+//! categories of a tenant (`?realm=`, the default plan without).
+//! `/api/users/:id/class-of-service` shows and assigns a user's class, and
+//! its `override` sub-resource gives the user another class until a given
+//! time. Authorization codes are never returned, only their labels.
+
+use super::cdr_dto::ApiResponse;
+use super::user_handler::AppState;
+use crate::domain::class_of_service::{
+    CategoryPlan, ClassOfServiceError, ClassOfServiceRegistry, ClassOverride, ServiceClass,
+};
+use crate::domain::user::User;
+use axum::{
+    extract::{Path, Query, State},
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    Json,
+};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+use tracing::{error, info, warn};
+
+#[derive(Debug, Default, Deserialize)]
+pub struct ClassesQuery {
+    /// Tenant realm; the default plan without
+    pub realm: Option<String>,
+}
+
+/// Classes of service of a tenant
+#[derive(Debug, Serialize)]
+pub struct ClassesResponse {
+    pub realm: Option<String>,
+    pub default_class: String,
+    pub classes: Vec<ServiceClass>,
+    pub categories: CategoryPlan,
+    /// Labels of the tenant's authorization codes
+    pub authorization_codes: Vec<String>,
+}
+
+/// Assign a class, or the tenant default with `null`
+#[derive(Debug, Deserialize)]
+pub struct AssignClassRequest {
+    pub class: Option<String>,
+}
+
+/// Temporary class of a user
+#[derive(Debug, Deserialize)]
+pub struct ClassOverrideRequest {
+    pub class: String,
+    pub until: DateTime<Utc>,
+}
+
+/// End override response
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ClearOverrideResponse {
+    pub username: String,
+    /// False when the user had no override
+    pub cleared: bool,
+}
+
+#[allow(clippy::result_large_err)]
+fn registry(state: &AppState) -> Result<&Arc<ClassOfServiceRegistry>, Response> {
+    state.class_of_service.as_ref().ok_or_else(|| {
+        error!("Class of service registry not available");
+        (
+            StatusCode::SERVICE_UNAVAILABLE,
+            Json(ApiResponse::<()>::error("Classes of service not enabled".to_string())),
+        )
+            .into_response()
+    })
+}
+
+async fn find_user(state: &AppState, id: i32) -> Result<User, Response> {
+    match state.user_repository.find_by_id(id).await {
+        Ok(Some(user)) => Ok(user),
+        Ok(None) => Err((
+            StatusCode::NOT_FOUND,
+            Json(ApiResponse::<()>::error(format!("User {} not found", id))),
+        )
+            .into_response()),
+        Err(e) => {
+            error!("API: Failed to get user: {}", e);
+            Err(StatusCode::INTERNAL_SERVER_ERROR.into_response())
+        }
+    }
+}
+
+fn refused(username: &str, e: ClassOfServiceError) -> Response {
+    warn!("API: Class of service change for {} refused: {}", username, e);
+    (
+        StatusCode::BAD_REQUEST,
+        Json(ApiResponse::<()>::error(e.to_string())),
+    )
+        .into_response()
+}
+
+/// Classes and categories of a tenant
+pub async fn list_classes(
+    State(state): State<AppState>,
+    Query(query): Query<ClassesQuery>,
+) -> Response {
+    let registry = match registry(&state) {
+        Ok(registry) => registry,
+        Err(response) => return response,
+    };
+    let plan = registry.plan_for(query.realm.as_deref());
+    Json(ApiResponse::success(ClassesResponse {
+        realm: query.realm,
+        default_class: plan.default_class,
+        classes: plan.classes,
+        categories: plan.categories,
+        authorization_codes: plan.authorization_codes.into_iter().map(|c| c.label).collect(),
+    }))
+    .into_response()
+}
+
+/// Class of service of a user
+pub async fn get_user_class(State(state): State<AppState>, Path(id): Path<i32>) -> Response {
+    let registry = match registry(&state) {
+        Ok(registry) => registry,
+        Err(response) => return response,
+    };
+    let user = match find_user(&state, id).await {
+        Ok(user) => user,
+        Err(response) => return response,
+    };
+
+    let status = registry.status(&user.username, Some(&user.realm), Utc::now());
+    Json(ApiResponse::success(status)).into_response()
+}
+
+/// Assign a user's class of service
+pub async fn assign_user_class(
+    State(state): State<AppState>,
+    Path(id): Path<i32>,
+    Json(request): Json<AssignClassRequest>,
+) -> Response {
+    let registry = match registry(&state) {
+        Ok(registry) => registry,
+        Err(response) => return response,
+    };
+    let user = match find_user(&state, id).await {
+        Ok(user) => user,
+        Err(response) => return response,
+    };
+
+    if let Err(e) = registry.assign(&user.username, Some(&user.realm), request.class) {
+        return refused(&user.username, e);
+    }
+    let status = registry.status(&user.username, Some(&user.realm), Utc::now());
+    Json(ApiResponse::success(status)).into_response()
+}
+
+/// Give a user another class until a given time
+pub async fn set_class_override(
+    State(state): State<AppState>,
+    Path(id): Path<i32>,
+    Json(request): Json<ClassOverrideRequest>,
+) -> Response {
+    let registry = match registry(&state) {
+        Ok(registry) => registry,
+        Err(response) => return response,
+    };
+    let user = match find_user(&state, id).await {
+        Ok(user) => user,
+        Err(response) => return response,
+    };
+
+    let now = Utc::now();
+    let class_override = ClassOverride {
+        class: request.class,
+        until: request.until,
+        granted_by: "api".to_string(),
+    };
+    if let Err(e) = registry.set_override(&user.username, Some(&user.realm), class_override, now) {
+        return refused(&user.username, e);
+    }
+    let status = registry.status(&user.username, Some(&user.realm), now);
+    Json(ApiResponse::success(status)).into_response()
+}
+
+/// End a user's override before it expires
+pub async fn clear_class_override(
+    State(state): State<AppState>,
+    Path(id): Path<i32>,
+) -> Response {
+    let registry = match registry(&state) {
+        Ok(registry) => registry,
+        Err(response) => return response,
+    };
+    let user = match find_user(&state, id).await {
+        Ok(user) => user,
+        Err(response) => return response,
+    };
+
+    info!("API: Ending class of service override of {}", user.username);
+    let cleared = registry.clear_override(&user.username);
+    Json(ApiResponse::success(ClearOverrideResponse {
+        username: user.username,
+        cleared,
+    }))
+    .into_response()
+}
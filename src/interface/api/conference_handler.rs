@@ -1,12 +1,19 @@
 /// Conference management REST API handlers
+use super::openapi::{
+    array, boolean, integer, nullable, number, object, one_of, reference, string, ApiSchema,
+};
 use super::user_handler::AppState;
-use crate::domain::conference::ParticipantRole;
+use crate::domain::conference::{
+    ConferenceRoom, EntryExitSettings, FloorControl, FloorState, Participant, ParticipantRole,
+};
+use crate::infrastructure::media::BandwidthEstimate;
 use axum::{
     extract::{Path, State},
     http::StatusCode,
     response::{IntoResponse, Json},
 };
 use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
 use tracing::{info, warn};
 use uuid::Uuid;
 
@@ -16,6 +23,12 @@ pub struct CreateConferenceRequest {
     pub name: String,
     pub pin: Option<String>,
     pub max_participants: Option<usize>,
+    /// Run as a lecture: participants join muted and talk when a
+    /// moderator grants them the floor
+    pub floor_control: Option<FloorControl>,
+    /// Tones or name announcements when participants join and leave
+    #[serde(default)]
+    pub entry_exit: Option<EntryExitSettings>,
 }
 
 /// Conference response
@@ -26,6 +39,62 @@ pub struct ConferenceResponse {
     pub has_pin: bool,
     pub max_participants: usize,
     pub participant_count: usize,
+    pub lecture_mode: bool,
+}
+
+impl From<&ConferenceRoom> for ConferenceResponse {
+    fn from(room: &ConferenceRoom) -> Self {
+        Self {
+            id: room.id.to_string(),
+            name: room.name.clone(),
+            has_pin: room.pin.is_some(),
+            max_participants: room.max_participants,
+            participant_count: room.participant_count(),
+            lecture_mode: room.is_lecture(),
+        }
+    }
+}
+
+/// Participant in the conference roster
+#[derive(Debug, Serialize)]
+pub struct ParticipantResponse {
+    pub id: String,
+    pub name: String,
+    pub call_id: String,
+    pub role: ParticipantRole,
+    pub is_muted: bool,
+    pub floor: FloorState,
+    /// Estimates of a WebRTC leg that negotiated REMB feedback
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub bandwidth: Option<BandwidthEstimate>,
+}
+
+impl From<&Participant> for ParticipantResponse {
+    fn from(participant: &Participant) -> Self {
+        Self {
+            id: participant.id.to_string(),
+            name: participant.name.clone(),
+            call_id: participant.call_id.clone(),
+            role: participant.role.clone(),
+            is_muted: participant.is_muted,
+            floor: participant.floor,
+            bandwidth: None,
+        }
+    }
+}
+
+/// Conference details with the roster
+#[derive(Debug, Serialize)]
+pub struct ConferenceDetailsResponse {
+    #[serde(flatten)]
+    pub conference: ConferenceResponse,
+    pub participants: Vec<ParticipantResponse>,
+}
+
+/// Raise hand response
+#[derive(Debug, Serialize)]
+pub struct RaiseHandResponse {
+    pub floor: FloorState,
 }
 
 /// Request to join a conference
@@ -35,6 +104,12 @@ pub struct JoinConferenceRequest {
     pub name: String,
     pub pin: Option<String>,
     pub role: Option<String>,
+    /// Recording of the participant saying their name, announced on join
+    #[serde(default)]
+    pub name_recording: Option<String>,
+    /// Mailbox whose recorded name announces an internal participant
+    #[serde(default)]
+    pub mailbox: Option<String>,
 }
 
 /// Join response
@@ -51,6 +126,127 @@ pub struct MuteRequest {
     pub call_id: String,
 }
 
+const FLOOR_STATES: &[&str] = &["listener", "hand_raised", "speaker"];
+
+impl ApiSchema for CreateConferenceRequest {
+    const NAME: &'static str = "CreateConferenceRequest";
+
+    fn schema() -> Value {
+        object(json!({
+            "name": string(),
+            "pin": nullable(string()),
+            "max_participants": nullable(integer()),
+            "floor_control": nullable(object(json!({
+                "moderator_pin": nullable(string()),
+                "max_speakers": nullable(integer()),
+                "silence_release_secs": nullable(integer()),
+            }))),
+            "entry_exit": nullable(object(json!({
+                "cue": nullable(one_of(&["silent", "tone", "name"])),
+                "name_threshold": nullable(integer()),
+                "duck_gain": nullable(number()),
+                "record_name_prompt": nullable(string()),
+                "name_max_secs": nullable(integer()),
+                "joined_prompt": nullable(string()),
+                "left_prompt": nullable(string()),
+            }))),
+        }))
+    }
+}
+
+impl ApiSchema for ConferenceResponse {
+    const NAME: &'static str = "Conference";
+
+    fn schema() -> Value {
+        object(json!({
+            "id": string(),
+            "name": string(),
+            "has_pin": boolean(),
+            "max_participants": integer(),
+            "participant_count": integer(),
+            "lecture_mode": boolean(),
+        }))
+    }
+}
+
+impl ApiSchema for ParticipantResponse {
+    const NAME: &'static str = "Participant";
+
+    fn schema() -> Value {
+        object(json!({
+            "id": string(),
+            "name": string(),
+            "call_id": string(),
+            "role": one_of(&["Moderator", "Presenter", "Attendee", "Listener"]),
+            "is_muted": boolean(),
+            "floor": one_of(FLOOR_STATES),
+            "bandwidth": nullable(object(json!({
+                "receive_bitrate": integer(),
+                "send_bitrate": integer(),
+                "loss": number(),
+                "jitter_ms": number(),
+                "capped": boolean(),
+            }))),
+        }))
+    }
+}
+
+impl ApiSchema for ConferenceDetailsResponse {
+    const NAME: &'static str = "ConferenceDetails";
+
+    fn schema() -> Value {
+        json!({
+            "allOf": [
+                reference::<ConferenceResponse>(),
+                object(json!({ "participants": array(reference::<ParticipantResponse>()) })),
+            ]
+        })
+    }
+}
+
+impl ApiSchema for RaiseHandResponse {
+    const NAME: &'static str = "RaiseHandResponse";
+
+    fn schema() -> Value {
+        object(json!({ "floor": one_of(FLOOR_STATES) }))
+    }
+}
+
+impl ApiSchema for JoinConferenceRequest {
+    const NAME: &'static str = "JoinConferenceRequest";
+
+    fn schema() -> Value {
+        object(json!({
+            "call_id": string(),
+            "name": string(),
+            "pin": nullable(string()),
+            "role": nullable(string()),
+            "name_recording": nullable(string()),
+            "mailbox": nullable(string()),
+        }))
+    }
+}
+
+impl ApiSchema for JoinConferenceResponse {
+    const NAME: &'static str = "JoinConferenceResponse";
+
+    fn schema() -> Value {
+        object(json!({
+            "room_id": string(),
+            "participant_id": string(),
+            "success": boolean(),
+        }))
+    }
+}
+
+impl ApiSchema for MuteRequest {
+    const NAME: &'static str = "ParticipantRequest";
+
+    fn schema() -> Value {
+        object(json!({ "call_id": string() }))
+    }
+}
+
 /// Create a new conference room
 pub async fn create_conference_room(
     State(state): State<AppState>,
@@ -68,11 +264,26 @@ pub async fn create_conference_room(
     };
 
     let max_participants = req.max_participants.unwrap_or(50);
+    let lecture_mode = req.floor_control.is_some();
 
-    match manager
+    let created = match manager
         .create_room(req.name.clone(), req.pin.clone(), max_participants)
         .await
     {
+        Ok(room_id) => {
+            let mut configured = Ok(());
+            if let Some(control) = req.floor_control {
+                configured = manager.enable_floor_control(room_id, control).await;
+            }
+            if let (Ok(()), Some(settings)) = (&configured, req.entry_exit) {
+                configured = manager.set_entry_exit(room_id, settings).await;
+            }
+            configured.map(|_| room_id)
+        }
+        Err(e) => Err(e),
+    };
+
+    match created {
         Ok(room_id) => {
             let response = ConferenceResponse {
                 id: room_id.to_string(),
@@ -80,6 +291,7 @@ pub async fn create_conference_room(
                 has_pin: req.pin.is_some(),
                 max_participants,
                 participant_count: 0,
+                lecture_mode,
             };
             (StatusCode::CREATED, Json(response)).into_response()
         }
@@ -95,6 +307,9 @@ pub async fn create_conference_room(
 }
 
 /// Join a conference room
+///
+/// In lecture mode the PIN decides the role: the moderator PIN makes a
+/// moderator and the room PIN a listener.
 pub async fn join_conference_room(
     State(state): State<AppState>,
     Path(room_id): Path<String>,
@@ -128,8 +343,15 @@ pub async fn join_conference_room(
         _ => ParticipantRole::Attendee,
     };
 
+    // A recording made at join wins over the mailbox's recorded name
+    let name_recording = match (req.name_recording, req.mailbox) {
+        (Some(recording), _) => Some(recording),
+        (None, Some(mailbox)) => manager.stored_name_recording(&mailbox).await,
+        (None, None) => None,
+    };
+
     match manager
-        .join_conference(room_uuid, req.call_id, req.name, role, req.pin)
+        .join_conference_named(room_uuid, req.call_id, req.name, role, req.pin, name_recording)
         .await
     {
         Ok(participant_id) => {
@@ -258,6 +480,93 @@ pub async fn end_conference(
     }
 }
 
+/// Raise or lower the hand of a participant in a lecture mode conference
+pub async fn raise_hand(
+    State(state): State<AppState>,
+    Json(req): Json<MuteRequest>,
+) -> impl IntoResponse {
+    info!("Raise hand request for call {}", req.call_id);
+
+    let Some(ref manager) = state.conference_manager else {
+        return StatusCode::SERVICE_UNAVAILABLE.into_response();
+    };
+
+    match manager.raise_hand(&req.call_id).await {
+        Ok(floor) => (StatusCode::OK, Json(RaiseHandResponse { floor })).into_response(),
+        Err(e) => {
+            warn!("Failed to raise hand: {}", e);
+            (
+                StatusCode::BAD_REQUEST,
+                Json(serde_json::json!({ "error": e })),
+            )
+                .into_response()
+        }
+    }
+}
+
+/// Give a participant the floor (moderator action)
+pub async fn grant_floor(
+    State(state): State<AppState>,
+    Path((room_id, participant_id)): Path<(String, String)>,
+) -> impl IntoResponse {
+    change_floor(state, room_id, participant_id, true).await
+}
+
+/// Take the floor back from a participant (moderator action)
+pub async fn revoke_floor(
+    State(state): State<AppState>,
+    Path((room_id, participant_id)): Path<(String, String)>,
+) -> impl IntoResponse {
+    change_floor(state, room_id, participant_id, false).await
+}
+
+async fn change_floor(
+    state: AppState,
+    room_id: String,
+    participant_id: String,
+    grant: bool,
+) -> axum::response::Response {
+    info!(
+        "{} floor request for participant {} in room {}",
+        if grant { "Grant" } else { "Revoke" },
+        participant_id,
+        room_id
+    );
+
+    let Some(ref manager) = state.conference_manager else {
+        return StatusCode::SERVICE_UNAVAILABLE.into_response();
+    };
+
+    let (room_uuid, participant_uuid) =
+        match (Uuid::parse_str(&room_id), Uuid::parse_str(&participant_id)) {
+            (Ok(room), Ok(participant)) => (room, participant),
+            _ => {
+                return (
+                    StatusCode::BAD_REQUEST,
+                    Json(serde_json::json!({ "error": "Invalid room or participant ID format" })),
+                )
+                    .into_response();
+            }
+        };
+
+    let result = if grant {
+        manager.grant_floor(room_uuid, participant_uuid).await
+    } else {
+        manager.revoke_floor(room_uuid, participant_uuid).await
+    };
+    match result {
+        Ok(_) => StatusCode::NO_CONTENT.into_response(),
+        Err(e) => {
+            warn!("Failed to change floor: {}", e);
+            (
+                StatusCode::BAD_REQUEST,
+                Json(serde_json::json!({ "error": e })),
+            )
+                .into_response()
+        }
+    }
+}
+
 /// Get conference details
 pub async fn get_conference_details(
     State(state): State<AppState>,
@@ -284,12 +593,18 @@ pub async fn get_conference_details(
 
     match manager.get_room(room_uuid).await {
         Ok(room) => {
-            let response = ConferenceResponse {
-                id: room.id.to_string(),
-                name: room.name.clone(),
-                has_pin: room.pin.is_some(),
-                max_participants: room.max_participants,
-                participant_count: room.participant_count(),
+            let mut participants: Vec<ParticipantResponse> = room
+                .participants
+                .values()
+                .map(|participant| ParticipantResponse {
+                    bandwidth: manager.bandwidth_estimate(&participant.call_id),
+                    ..ParticipantResponse::from(participant)
+                })
+                .collect();
+            participants.sort_by(|a, b| a.name.cmp(&b.name));
+            let response = ConferenceDetailsResponse {
+                conference: ConferenceResponse::from(&room),
+                participants,
             };
             (StatusCode::OK, Json(response)).into_response()
         }
@@ -315,16 +630,7 @@ pub async fn list_active_conferences(State(state): State<AppState>) -> impl Into
     };
 
     let rooms = manager.list_active_conferences().await;
-    let responses: Vec<ConferenceResponse> = rooms
-        .into_iter()
-        .map(|room| ConferenceResponse {
-            id: room.id.to_string(),
-            name: room.name.clone(),
-            has_pin: room.pin.is_some(),
-            max_participants: room.max_participants,
-            participant_count: room.participant_count(),
-        })
-        .collect();
+    let responses: Vec<ConferenceResponse> = rooms.iter().map(ConferenceResponse::from).collect();
 
     (StatusCode::OK, Json(responses)).into_response()
 }
@@ -0,0 +1,45 @@
+//! Configuration report API handler
+//!
+//! `GET /api/admin/config-report` returns the findings of the preflight
+//! checks run at startup, so warnings that did not stop the server can be
+//! seen without reading its log. Credentials are checked as for
+//! diagnostics.
+
+use super::cdr_dto::ApiResponse;
+use super::diagnostics_handler::{authorize, client_ip};
+use super::user_handler::AppState;
+use axum::{
+    extract::State,
+    http::{HeaderMap, StatusCode},
+    response::{IntoResponse, Response},
+    Json,
+};
+use tracing::error;
+
+fn unavailable(what: &str) -> Response {
+    error!("{} not available", what);
+    (
+        StatusCode::SERVICE_UNAVAILABLE,
+        Json(ApiResponse::<()>::error(format!("{} not available", what))),
+    )
+        .into_response()
+}
+
+/// Last preflight report (requires `system:config`)
+pub async fn get_config_report(State(state): State<AppState>, headers: HeaderMap) -> Response {
+    let Some(diagnostics) = state.diagnostics.clone() else {
+        return unavailable("Admin authentication");
+    };
+    let Some(report) = state.config_report.clone() else {
+        return unavailable("Configuration report");
+    };
+
+    let ip = client_ip(&headers);
+    if let Err(response) =
+        authorize(&state, &diagnostics, &headers, &ip, "config_report", "get").await
+    {
+        return response;
+    }
+
+    Json(ApiResponse::success(report.as_ref().clone())).into_response()
+}
@@ -0,0 +1,290 @@
+//! Provisioned device API handlers
+//!
+//! Phones fetch their configuration at `GET /provisioning/:mac`, with their
+//! device token (`?token=` or `Authorization: Bearer`): the user's account,
+//! display name and speed dials, in the format of the device's vendor.
+//! `POST /api/devices/:mac/resync` tells a phone to fetch it again, and
+//! `GET /api/devices/:mac` shows when it was last told to and whether it did.
+
+use super::cdr_dto::ApiResponse;
+use super::directory_handler::{authenticate_device, request_token, xml_escape};
+use super::user_handler::AppState;
+use crate::domain::device_provisioning::{normalize_mac, DeviceProfile, DeviceResyncStatus, DeviceVendor};
+use crate::domain::speed_dial::{effective_speed_dials, SpeedDial};
+use crate::domain::user::User;
+use crate::infrastructure::protocols::sip::{DeviceResyncer, ResyncOutcome};
+use axum::{
+    extract::{Path, Query, State},
+    http::{header, HeaderMap, StatusCode},
+    response::{IntoResponse, Response},
+    Json,
+};
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+use tracing::{error, info, warn};
+
+/// First line key used for speed dials; key 1 is the line itself
+const FIRST_SPEED_DIAL_KEY: usize = 2;
+
+#[derive(Debug, Deserialize)]
+pub struct ProvisioningQuery {
+    /// Device token, for phones that cannot send headers
+    pub token: Option<String>,
+}
+
+/// Result of a resync request
+#[derive(Debug, Serialize)]
+pub struct ResyncResponse {
+    /// `sent`, or `queued` until the device registers
+    pub outcome: &'static str,
+    /// Contacts the NOTIFY was sent to
+    pub contacts: usize,
+    pub device: DeviceResyncStatus,
+}
+
+fn resync_unavailable() -> Response {
+    (
+        StatusCode::SERVICE_UNAVAILABLE,
+        Json(ApiResponse::<()>::error("Device provisioning not available".to_string())),
+    )
+        .into_response()
+}
+
+fn device_not_found(mac: &str) -> Response {
+    (
+        StatusCode::NOT_FOUND,
+        Json(ApiResponse::<()>::error(format!("Device {} not found", mac))),
+    )
+        .into_response()
+}
+
+/// Resync state of a device
+pub async fn get_device(State(state): State<AppState>, Path(mac): Path<String>) -> Response {
+    let resyncer = match &state.device_resync {
+        Some(resyncer) => resyncer,
+        None => return resync_unavailable(),
+    };
+    match resyncer.status(&mac) {
+        Some(status) => Json(ApiResponse::success(status)).into_response(),
+        None => device_not_found(&mac),
+    }
+}
+
+/// Tell a device to fetch its configuration again
+pub async fn resync_device(State(state): State<AppState>, Path(mac): Path<String>) -> Response {
+    let resyncer = match &state.device_resync {
+        Some(resyncer) => resyncer,
+        None => return resync_unavailable(),
+    };
+
+    info!("API: Resync of device {}", mac);
+    let (outcome, contacts) = match resyncer.resync(&mac).await {
+        Ok(ResyncOutcome::Sent { contacts }) => ("sent", contacts),
+        Ok(ResyncOutcome::Queued) => ("queued", 0),
+        Err(_) => return device_not_found(&mac),
+    };
+    let device = match resyncer.status(&mac) {
+        Some(device) => device,
+        None => return device_not_found(&mac),
+    };
+
+    (
+        StatusCode::ACCEPTED,
+        Json(ApiResponse::success(ResyncResponse {
+            outcome,
+            contacts,
+            device,
+        })),
+    )
+        .into_response()
+}
+
+/// Configuration file of a device
+pub async fn get_provisioning_config(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Path(mac): Path<String>,
+    Query(query): Query<ProvisioningQuery>,
+) -> Response {
+    let identity = match request_token(&headers, query.token.as_deref()) {
+        Some(token) => match authenticate_device(&state, &token) {
+            Ok(identity) => identity,
+            Err(response) => return response,
+        },
+        None => return (StatusCode::UNAUTHORIZED, "Device token required").into_response(),
+    };
+    // A token only opens its own device's configuration
+    match (normalize_mac(&identity.device_id), normalize_mac(&mac)) {
+        (Some(own), Some(requested)) if own == requested => {}
+        _ => {
+            warn!("API: Device {} asked for the configuration of {}", identity.device_id, mac);
+            return StatusCode::FORBIDDEN.into_response();
+        }
+    }
+
+    let resyncer = match &state.device_resync {
+        Some(resyncer) => resyncer,
+        None => return resync_unavailable(),
+    };
+    let device = match resyncer.device(&mac) {
+        Some(device) => device.clone(),
+        None => return device_not_found(&mac),
+    };
+
+    let user = match state.user_repository.find_by_username(&device.username).await {
+        Ok(Some(user)) => user,
+        Ok(None) => {
+            warn!("API: Device {} belongs to unknown user {}", mac, device.username);
+            return device_not_found(&mac);
+        }
+        Err(e) => {
+            error!("API: Failed to get user: {}", e);
+            return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+        }
+    };
+    let speed_dials = match &state.speed_dial_repository {
+        Some(repo) => {
+            let personal = repo.list_for_user(user.id).await;
+            let company = repo.list_company().await;
+            match (personal, company) {
+                (Ok(personal), Ok(company)) => effective_speed_dials(&personal, &company),
+                (Err(e), _) | (_, Err(e)) => {
+                    error!("API: Speed dial database error: {}", e);
+                    return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+                }
+            }
+        }
+        None => Vec::new(),
+    };
+
+    resyncer.config_fetched(&mac);
+    info!("API: Configuration fetched by device {}", mac);
+
+    let (content_type, body) = match device.vendor {
+        DeviceVendor::Polycom => ("application/xml; charset=utf-8", render_polycom(&device, &user)),
+        DeviceVendor::Yealink | DeviceVendor::Generic => (
+            "text/plain; charset=utf-8",
+            render_yealink(&device, &user, &speed_dials),
+        ),
+    };
+    ([(header::CONTENT_TYPE, content_type)], body).into_response()
+}
+
+/// Resync the phones of `user_id` (all phones for `None`) after a
+/// successful change to what their configuration carries
+pub(super) async fn resync_after(state: &AppState, user_id: Option<i32>, response: Response) -> Response {
+    if response.status().is_success() {
+        if let Some(resyncer) = &state.device_resync {
+            schedule_resync(state, resyncer, user_id).await;
+        }
+    }
+    response
+}
+
+async fn schedule_resync(state: &AppState, resyncer: &Arc<DeviceResyncer>, user_id: Option<i32>) {
+    match user_id {
+        Some(id) => match state.user_repository.find_by_id(id).await {
+            Ok(Some(user)) => resyncer.schedule_user(&user.username),
+            Ok(None) => {}
+            Err(e) => warn!("API: Cannot resync devices of user {}: {}", id, e),
+        },
+        None => resyncer.schedule_all(),
+    }
+}
+
+fn display_name(user: &User) -> &str {
+    user.display_name.as_deref().unwrap_or(&user.username)
+}
+
+/// Yealink `.cfg` key/value configuration; speed dials go on line keys
+pub fn render_yealink(device: &DeviceProfile, user: &User, speed_dials: &[SpeedDial]) -> String {
+    let name = display_name(user);
+    let mut cfg = String::from("#!version:1.0.0.1\n");
+    cfg.push_str("account.1.enable = 1\n");
+    cfg.push_str(&format!("account.1.label = {}\n", name));
+    cfg.push_str(&format!("account.1.display_name = {}\n", name));
+    cfg.push_str(&format!("account.1.user_name = {}\n", user.username));
+    cfg.push_str(&format!("account.1.auth_name = {}\n", user.username));
+    cfg.push_str(&format!("account.1.sip_server.1.address = {}\n", device.realm));
+    for (i, speed_dial) in speed_dials.iter().enumerate() {
+        let key = FIRST_SPEED_DIAL_KEY + i;
+        // Type 13 is a speed dial; the PBX resolves the code
+        cfg.push_str(&format!("linekey.{}.type = 13\n", key));
+        cfg.push_str(&format!("linekey.{}.value = {}\n", key, speed_dial.code));
+        cfg.push_str(&format!(
+            "linekey.{}.label = {}\n",
+            key,
+            speed_dial.label.as_deref().unwrap_or(&speed_dial.target)
+        ));
+    }
+    cfg
+}
+
+/// Poly `polycomConfig` registration; Poly phones take speed dials from
+/// the directory (`/directory/poly.xml`)
+pub fn render_polycom(device: &DeviceProfile, user: &User) -> String {
+    let name = xml_escape(display_name(user));
+    format!(
+        "<?xml version=\"1.0\" encoding=\"UTF-8\" standalone=\"yes\"?>\n\
+         <polycomConfig>\n \
+         <reg reg.1.address=\"{user}@{realm}\" reg.1.auth.userId=\"{user}\" \
+         reg.1.displayName=\"{name}\" reg.1.label=\"{name}\" \
+         reg.1.server.1.address=\"{realm}\"/>\n\
+         </polycomConfig>\n",
+        user = xml_escape(&user.username),
+        realm = xml_escape(&device.realm),
+        name = name,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::Utc;
+
+    fn user(display_name: &str) -> User {
+        User {
+            id: 7,
+            username: "1001".to_string(),
+            password_hash: String::new(),
+            sip_ha1: None,
+            realm: "example.com".to_string(),
+            display_name: Some(display_name.to_string()),
+            email: None,
+            department: None,
+            locale: None,
+            timezone: None,
+            enabled: true,
+            role_id: None,
+            created_at: Utc::now(),
+            updated_at: Utc::now(),
+        }
+    }
+
+    fn device(vendor: DeviceVendor) -> DeviceProfile {
+        DeviceProfile {
+            mac: "805ec0aabbcc".to_string(),
+            vendor,
+            username: "1001".to_string(),
+            realm: "example.com".to_string(),
+        }
+    }
+
+    #[test]
+    fn test_render_per_vendor() {
+        let speed_dials = vec![
+            SpeedDial::new(Some(7), "*1".to_string(), "1000".to_string(), Some("Reception".to_string())),
+            SpeedDial::new(None, "*2".to_string(), "+15551234567".to_string(), None),
+        ];
+        let cfg = render_yealink(&device(DeviceVendor::Yealink), &user("Alice Smith"), &speed_dials);
+        assert!(cfg.starts_with("#!version:1.0.0.1\n"));
+        assert!(cfg.contains("account.1.display_name = Alice Smith\n"));
+        assert!(cfg.contains("account.1.user_name = 1001\n"));
+        assert!(cfg.contains("linekey.2.value = *1\nlinekey.2.label = Reception\n"));
+        assert!(cfg.contains("linekey.3.label = +15551234567\n"));
+
+        let xml = render_polycom(&device(DeviceVendor::Polycom), &user("Smith & Sons"));
+        assert!(xml.contains("reg.1.address=\"1001@example.com\""));
+        assert!(xml.contains("reg.1.displayName=\"Smith &amp; Sons\""));
+    }
+}
@@ -0,0 +1,561 @@
+//! Diagnostic bundle export
+//!
+//! `GET /api/admin/diagnostics` returns a tar.gz with everything usually
+//! asked for when a problem is reported: the effective configuration with
+//! secrets masked, registrations, active calls, SIP state counts, the
+//! effective transaction timers per transport and trunk, a metrics
+//! snapshot, runtime task counts, recent log lines and build information.
+//! The archive is compressed on a blocking thread and streamed to the
+//! client as it is written.
+
+use super::cdr_dto::ApiResponse;
+use super::user_handler::{AppState, BindingInfo, RegistrationInfo};
+use crate::config::Redact;
+use crate::domain::security::{SecurityAuditLogger, SecurityEvent, SecuritySeverity};
+use crate::domain::user::{Permission, RoleRepository, User};
+use crate::infrastructure::logging::LogRingBuffer;
+use crate::infrastructure::protocols::sip::TransactionLayer;
+use axum::{
+    body::Body,
+    extract::{Query, State},
+    http::{header, HeaderMap, StatusCode},
+    response::{IntoResponse, Response},
+    Json,
+};
+use base64::Engine;
+use bytes::Bytes;
+use chrono::Utc;
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use metrics_exporter_prometheus::PrometheusHandle;
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::io::{self, Write};
+use std::sync::Arc;
+use tokio::sync::mpsc;
+use tracing::{error, info, warn};
+
+/// Log lines included when the request does not say
+pub const DEFAULT_DIAGNOSTIC_LOG_LINES: usize = 500;
+
+/// Directory all bundle entries are placed under
+const BUNDLE_ROOT: &str = "yakyak-diagnostics";
+
+/// Compressed chunks buffered ahead of the client
+const STREAM_BUFFER_CHUNKS: usize = 16;
+
+/// Sources for the diagnostic bundle beyond the shared API state
+pub struct DiagnosticsContext {
+    /// Effective configuration, redacted when the context is built
+    config: serde_json::Value,
+    log_buffer: Option<Arc<LogRingBuffer>>,
+    prometheus: Option<PrometheusHandle>,
+    role_repository: Option<Arc<dyn RoleRepository>>,
+    audit_logger: Option<Arc<SecurityAuditLogger>>,
+    transaction_layer: Option<Arc<TransactionLayer>>,
+}
+
+impl DiagnosticsContext {
+    pub fn new(config: &impl Redact) -> Self {
+        Self {
+            config: config.redacted(),
+            log_buffer: None,
+            prometheus: None,
+            role_repository: None,
+            audit_logger: None,
+            transaction_layer: None,
+        }
+    }
+
+    pub fn with_log_buffer(mut self, log_buffer: Arc<LogRingBuffer>) -> Self {
+        self.log_buffer = Some(log_buffer);
+        self
+    }
+
+    pub fn with_prometheus(mut self, prometheus: PrometheusHandle) -> Self {
+        self.prometheus = Some(prometheus);
+        self
+    }
+
+    /// Roles are needed to grant access; without them every request is refused
+    pub fn with_role_repository(mut self, role_repository: Arc<dyn RoleRepository>) -> Self {
+        self.role_repository = Some(role_repository);
+        self
+    }
+
+    pub fn with_audit_logger(mut self, audit_logger: Arc<SecurityAuditLogger>) -> Self {
+        self.audit_logger = Some(audit_logger);
+        self
+    }
+
+    pub fn with_transaction_layer(mut self, transaction_layer: Arc<TransactionLayer>) -> Self {
+        self.transaction_layer = Some(transaction_layer);
+        self
+    }
+
+    pub(super) fn audit(&self, event: SecurityEvent, severity: SecuritySeverity) {
+        if let Some(audit_logger) = &self.audit_logger {
+            audit_logger.log(event, severity);
+        }
+    }
+}
+
+/// Query parameters for the diagnostics download
+#[derive(Debug, Deserialize)]
+pub struct DiagnosticsQuery {
+    /// Number of recent log lines to include
+    pub log_lines: Option<usize>,
+}
+
+/// A file in the bundle
+#[derive(Debug, Clone)]
+pub struct BundleEntry {
+    /// Path inside the bundle directory
+    pub path: String,
+    pub contents: Vec<u8>,
+}
+
+impl BundleEntry {
+    pub fn text(path: &str, contents: String) -> Self {
+        Self {
+            path: path.to_string(),
+            contents: contents.into_bytes(),
+        }
+    }
+
+    pub fn json<T: Serialize>(path: &str, value: &T) -> Self {
+        Self {
+            path: path.to_string(),
+            contents: serde_json::to_vec_pretty(value).unwrap_or_default(),
+        }
+    }
+}
+
+/// Build, version and feature information
+#[derive(Debug, Serialize)]
+pub struct VersionInfo {
+    pub name: &'static str,
+    pub version: &'static str,
+    pub features: Vec<&'static str>,
+    pub debug_build: bool,
+    pub generated_at: String,
+}
+
+impl VersionInfo {
+    pub fn current() -> Self {
+        let mut features = Vec::new();
+        if cfg!(feature = "postgres") {
+            features.push("postgres");
+        }
+        if cfg!(feature = "memory") {
+            features.push("memory");
+        }
+        Self {
+            name: env!("CARGO_PKG_NAME"),
+            version: env!("CARGO_PKG_VERSION"),
+            features,
+            debug_build: cfg!(debug_assertions),
+            generated_at: Utc::now().to_rfc3339(),
+        }
+    }
+}
+
+/// SIP layer counts
+#[derive(Debug, Serialize)]
+pub struct SipStateSummary {
+    pub active_calls: Option<usize>,
+    pub dialogs: Option<usize>,
+    /// Absent when no transaction layer is attached
+    pub transactions: Option<usize>,
+    pub registered_aors: Option<usize>,
+}
+
+/// Tokio runtime task counts
+#[derive(Debug, Serialize)]
+pub struct RuntimeSummary {
+    pub workers: usize,
+    pub alive_tasks: usize,
+    pub global_queue_depth: usize,
+}
+
+/// Download the diagnostic bundle (requires `system:config`)
+pub async fn download_diagnostics(
+    State(state): State<AppState>,
+    Query(query): Query<DiagnosticsQuery>,
+    headers: HeaderMap,
+) -> Response {
+    let diagnostics = match &state.diagnostics {
+        Some(diagnostics) => diagnostics.clone(),
+        None => {
+            error!("Diagnostics not configured");
+            return (
+                StatusCode::SERVICE_UNAVAILABLE,
+                Json(ApiResponse::<()>::error("Diagnostics not available".to_string())),
+            )
+                .into_response();
+        }
+    };
+
+    let ip = client_ip(&headers);
+    let username =
+        match authorize(&state, &diagnostics, &headers, &ip, "diagnostics", "download").await {
+            Ok(username) => username,
+            Err(response) => return response,
+        };
+
+    info!("API: Diagnostic bundle requested by {}", username);
+    diagnostics.audit(
+        SecurityEvent::AdminAction {
+            admin_username: username,
+            ip,
+            action: "download".to_string(),
+            target: "diagnostics".to_string(),
+        },
+        SecuritySeverity::Medium,
+    );
+
+    let log_lines = query.log_lines.unwrap_or(DEFAULT_DIAGNOSTIC_LOG_LINES);
+    let entries = collect_entries(&state, &diagnostics, log_lines).await;
+    let filename = format!("yakyak-diagnostics-{}.tar.gz", Utc::now().format("%Y%m%d-%H%M%S"));
+
+    Response::builder()
+        .status(StatusCode::OK)
+        .header(header::CONTENT_TYPE, "application/gzip")
+        .header(
+            header::CONTENT_DISPOSITION,
+            format!("attachment; filename=\"{}\"", filename),
+        )
+        .body(Body::from_stream(stream_bundle(entries)))
+        .unwrap()
+}
+
+/// Check HTTP Basic credentials; returns the user and the permissions of
+/// its role (none without a role)
+pub(super) async fn authenticate(
+    state: &AppState,
+    diagnostics: &DiagnosticsContext,
+    headers: &HeaderMap,
+) -> Result<(User, HashSet<Permission>), Response> {
+    let unauthorized = || {
+        (
+            StatusCode::UNAUTHORIZED,
+            [(header::WWW_AUTHENTICATE, "Basic realm=\"yakyak\"")],
+            Json(ApiResponse::<()>::error("Authentication required".to_string())),
+        )
+            .into_response()
+    };
+
+    let (username, password) = basic_credentials(headers).ok_or_else(unauthorized)?;
+    let user = match state.user_repository.verify_credentials(&username, &password).await {
+        Ok(Some(user)) => user,
+        Ok(None) => return Err(unauthorized()),
+        Err(e) => {
+            error!("API: Failed to verify credentials: {}", e);
+            return Err(StatusCode::INTERNAL_SERVER_ERROR.into_response());
+        }
+    };
+
+    let permissions = match (&diagnostics.role_repository, user.role_id) {
+        (Some(roles), Some(role_id)) => match roles.get_by_id(role_id).await {
+            Ok(role) => role.map(|r| r.permissions).unwrap_or_default(),
+            Err(e) => {
+                error!("API: Failed to load role {}: {}", role_id, e);
+                return Err(StatusCode::INTERNAL_SERVER_ERROR.into_response());
+            }
+        },
+        _ => HashSet::new(),
+    };
+
+    Ok((user, permissions))
+}
+
+/// Check HTTP Basic credentials and the `system:config` permission for
+/// `action` on an admin `resource`
+pub(super) async fn authorize(
+    state: &AppState,
+    diagnostics: &DiagnosticsContext,
+    headers: &HeaderMap,
+    ip: &str,
+    resource: &str,
+    action: &str,
+) -> Result<String, Response> {
+    authorize_with(state, diagnostics, headers, ip, Permission::SystemConfig, resource, action)
+        .await
+}
+
+/// [`authorize`], requiring `permission` instead
+pub(super) async fn authorize_with(
+    state: &AppState,
+    diagnostics: &DiagnosticsContext,
+    headers: &HeaderMap,
+    ip: &str,
+    permission: Permission,
+    resource: &str,
+    action: &str,
+) -> Result<String, Response> {
+    let (user, permissions) = authenticate(state, diagnostics, headers).await?;
+    let permitted = permissions.contains(&permission);
+
+    if !permitted {
+        warn!("API: {} denied access to {}", user.username, resource);
+        diagnostics.audit(
+            SecurityEvent::PermissionDenied {
+                username: user.username,
+                ip: ip.to_string(),
+                resource: resource.to_string(),
+                action: action.to_string(),
+            },
+            SecuritySeverity::Medium,
+        );
+        return Err((
+            StatusCode::FORBIDDEN,
+            Json(ApiResponse::<()>::error(format!(
+                "Permission {} required",
+                permission.as_str()
+            ))),
+        )
+            .into_response());
+    }
+
+    Ok(user.username)
+}
+
+/// Username and password from an `Authorization: Basic` header
+pub(super) fn basic_credentials(headers: &HeaderMap) -> Option<(String, String)> {
+    let value = headers.get(header::AUTHORIZATION)?.to_str().ok()?;
+    let encoded = value.strip_prefix("Basic ")?;
+    let decoded = base64::engine::general_purpose::STANDARD
+        .decode(encoded.trim())
+        .ok()?;
+    let (username, password) = std::str::from_utf8(&decoded).ok()?.split_once(':')?;
+    Some((username.to_string(), password.to_string()))
+}
+
+pub(super) fn client_ip(headers: &HeaderMap) -> String {
+    headers
+        .get("x-forwarded-for")
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.split(',').next())
+        .map(|v| v.trim().to_string())
+        .unwrap_or_else(|| "unknown".to_string())
+}
+
+/// Gather the bundle contents
+async fn collect_entries(
+    state: &AppState,
+    diagnostics: &DiagnosticsContext,
+    log_lines: usize,
+) -> Vec<BundleEntry> {
+    let mut entries = vec![
+        BundleEntry::json("version.json", &VersionInfo::current()),
+        BundleEntry::json("config.json", &diagnostics.config),
+    ];
+
+    let registrations = match &state.registrar {
+        Some(registrar) => Some(registrar.get_all_registrations().await),
+        None => None,
+    };
+    let active_calls = match &state.call_router {
+        Some(router) => Some(router.get_active_calls().await),
+        None => None,
+    };
+    let dialogs = match &state.call_router {
+        Some(router) => Some(router.dialog_manager().count().await),
+        None => None,
+    };
+    let transactions = match &diagnostics.transaction_layer {
+        Some(layer) => Some(layer.transaction_count().await),
+        None => None,
+    };
+
+    entries.push(BundleEntry::json(
+        "sip_state.json",
+        &SipStateSummary {
+            active_calls: active_calls.as_ref().map(|calls| calls.len()),
+            dialogs,
+            transactions,
+            registered_aors: registrations.as_ref().map(|r| r.len()),
+        },
+    ));
+    if let Some(layer) = &diagnostics.transaction_layer {
+        entries.push(BundleEntry::json(
+            "sip_timers.json",
+            &layer.timer_config().effective(),
+        ));
+    }
+
+    let registrations: Vec<RegistrationInfo> = registrations
+        .unwrap_or_default()
+        .into_iter()
+        .map(|reg| RegistrationInfo {
+            aor: reg.aor,
+            bindings: reg
+                .bindings
+                .into_iter()
+                .map(|b| BindingInfo {
+                    contact: b.contact,
+                    expires_at: b.expires_at.to_rfc3339(),
+                    user_agent: b.user_agent,
+                })
+                .collect(),
+        })
+        .collect();
+    entries.push(BundleEntry::json("registrations.json", &registrations));
+    entries.push(BundleEntry::json("active_calls.json", &active_calls.unwrap_or_default()));
+    if let Some(linter) = &state.routing_linter {
+        entries.push(BundleEntry::json("routing_lint.json", &linter.lint().await));
+    }
+
+    if let Some(prometheus) = &diagnostics.prometheus {
+        entries.push(BundleEntry::text("metrics.prom", prometheus.render()));
+    }
+
+    let runtime = tokio::runtime::Handle::current().metrics();
+    entries.push(BundleEntry::json(
+        "runtime.json",
+        &RuntimeSummary {
+            workers: runtime.num_workers(),
+            alive_tasks: runtime.num_alive_tasks(),
+            global_queue_depth: runtime.global_queue_depth(),
+        },
+    ));
+
+    if let Some(log_buffer) = &diagnostics.log_buffer {
+        let mut logs = log_buffer.tail(log_lines).join("\n");
+        logs.push('\n');
+        entries.push(BundleEntry::text("logs.txt", logs));
+    }
+
+    entries
+}
+
+/// Write `entries` as a gzip-compressed tar archive
+pub fn write_bundle<W: Write>(writer: W, entries: &[BundleEntry]) -> io::Result<W> {
+    let mtime = Utc::now().timestamp().max(0) as u64;
+    let mut archive = tar::Builder::new(GzEncoder::new(writer, Compression::default()));
+    for entry in entries {
+        let mut header = tar::Header::new_gnu();
+        header.set_size(entry.contents.len() as u64);
+        header.set_mode(0o644);
+        header.set_mtime(mtime);
+        archive.append_data(
+            &mut header,
+            format!("{}/{}", BUNDLE_ROOT, entry.path),
+            entry.contents.as_slice(),
+        )?;
+    }
+    archive.into_inner()?.finish()
+}
+
+/// Compress the bundle on a blocking thread, yielding chunks as written
+fn stream_bundle(
+    entries: Vec<BundleEntry>,
+) -> impl futures::Stream<Item = Result<Bytes, io::Error>> {
+    let (tx, rx) = mpsc::channel(STREAM_BUFFER_CHUNKS);
+    tokio::task::spawn_blocking(move || {
+        let writer = ChunkWriter { tx: tx.clone() };
+        if let Err(e) = write_bundle(writer, &entries) {
+            warn!("Diagnostic bundle aborted: {}", e);
+            let _ = tx.blocking_send(Err(e));
+        }
+    });
+    futures::stream::unfold(rx, |mut rx| async move {
+        rx.recv().await.map(|chunk| (chunk, rx))
+    })
+}
+
+/// `Write` adapter sending each write as a chunk to the response stream
+struct ChunkWriter {
+    tx: mpsc::Sender<Result<Bytes, io::Error>>,
+}
+
+impl Write for ChunkWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.tx
+            .blocking_send(Ok(Bytes::copy_from_slice(buf)))
+            .map_err(|_| io::Error::new(io::ErrorKind::BrokenPipe, "client went away"))?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Config;
+    use flate2::read::GzDecoder;
+    use futures::StreamExt;
+    use std::collections::HashMap;
+    use std::io::Read;
+
+    fn unpack(archive: &[u8]) -> HashMap<String, String> {
+        let mut files = HashMap::new();
+        let mut archive = tar::Archive::new(GzDecoder::new(archive));
+        for entry in archive.entries().unwrap() {
+            let mut entry = entry.unwrap();
+            let path = entry.path().unwrap().to_string_lossy().to_string();
+            let mut contents = String::new();
+            entry.read_to_string(&mut contents).unwrap();
+            files.insert(path, contents);
+        }
+        files
+    }
+
+    #[tokio::test]
+    async fn test_streamed_bundle_contents() {
+        let mut config = Config::default();
+        config.database.url = "postgres://yakyak:s3cr3t@db:5432/yakyak".to_string();
+        let log_buffer = Arc::new(LogRingBuffer::new(10));
+        log_buffer.push("INFO first".to_string());
+        log_buffer.push("WARN second".to_string());
+        let diagnostics = DiagnosticsContext::new(&config).with_log_buffer(log_buffer.clone());
+
+        let entries = vec![
+            BundleEntry::json("version.json", &VersionInfo::current()),
+            BundleEntry::json("config.json", &diagnostics.config),
+            BundleEntry::text("logs.txt", log_buffer.tail(1).join("\n")),
+        ];
+        let mut archive = Vec::new();
+        let mut stream = Box::pin(stream_bundle(entries));
+        while let Some(chunk) = stream.next().await {
+            archive.extend_from_slice(&chunk.unwrap());
+        }
+
+        let files = unpack(&archive);
+        let mut names: Vec<_> = files.keys().cloned().collect();
+        names.sort();
+        assert_eq!(
+            names,
+            vec![
+                "yakyak-diagnostics/config.json",
+                "yakyak-diagnostics/logs.txt",
+                "yakyak-diagnostics/version.json",
+            ]
+        );
+
+        let config_json = &files["yakyak-diagnostics/config.json"];
+        assert!(config_json.contains("postgres://yakyak:********@db:5432/yakyak"));
+        assert!(!config_json.contains("s3cr3t"));
+        assert_eq!(files["yakyak-diagnostics/logs.txt"], "WARN second");
+
+        let version: serde_json::Value =
+            serde_json::from_str(&files["yakyak-diagnostics/version.json"]).unwrap();
+        assert_eq!(version["version"], env!("CARGO_PKG_VERSION"));
+    }
+
+    #[test]
+    fn test_basic_credentials() {
+        let mut headers = HeaderMap::new();
+        assert_eq!(basic_credentials(&headers), None);
+
+        // admin:pa:ss
+        headers.insert(header::AUTHORIZATION, "Basic YWRtaW46cGE6c3M=".parse().unwrap());
+        assert_eq!(
+            basic_credentials(&headers),
+            Some(("admin".to_string(), "pa:ss".to_string()))
+        );
+    }
+}
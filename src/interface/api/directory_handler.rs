@@ -0,0 +1,287 @@
+//! Phone directory API
+//!
+//! `GET /api/directory?q=` searches enabled users by name, extension or
+//! department. The same search is rendered for phones' remote phonebooks at
+//! `/directory/yealink.xml` and `/directory/poly.xml`. Phones authenticate
+//! with their device token (`?token=` or `Authorization: Bearer`), which
+//! also limits results to the device's realm; the JSON API takes an
+//! optional `realm` when called without a token.
+
+use super::cdr_dto::ApiResponse;
+use super::user_handler::AppState;
+use crate::domain::device_token::DeviceIdentity;
+use crate::domain::user::{DirectoryQuery, User};
+use axum::{
+    extract::{Query, State},
+    http::{header, HeaderMap, StatusCode},
+    response::{IntoResponse, Response},
+    Json,
+};
+use serde::{Deserialize, Serialize};
+use tracing::{error, info, warn};
+
+/// Directory search parameters
+#[derive(Debug, Deserialize)]
+pub struct DirectoryParams {
+    #[serde(default)]
+    pub q: String,
+    pub realm: Option<String>,
+    #[serde(default = "default_limit")]
+    pub limit: i64,
+    #[serde(default)]
+    pub offset: i64,
+    /// Device token, for phones that cannot send headers
+    pub token: Option<String>,
+}
+
+fn default_limit() -> i64 {
+    50
+}
+
+/// One directory result
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DirectoryEntry {
+    pub display_name: String,
+    pub extension: String,
+    pub department: Option<String>,
+    /// `online` or `offline` by registration, when known
+    pub presence: Option<String>,
+}
+
+/// Page of directory results
+#[derive(Debug, Serialize, Deserialize)]
+pub struct DirectoryPage {
+    pub entries: Vec<DirectoryEntry>,
+    pub limit: i64,
+    pub offset: i64,
+    /// Offset of the next page, if there is one
+    pub next_offset: Option<i64>,
+}
+
+/// Search the directory as JSON
+pub async fn search_directory(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Query(params): Query<DirectoryParams>,
+) -> Response {
+    let realm = match request_token(&headers, params.token.as_deref()) {
+        Some(token) => match authenticate_device(&state, &token) {
+            Ok(device) => Some(device.realm),
+            Err(response) => return response,
+        },
+        None => params.realm.clone(),
+    };
+
+    match lookup(&state, &params, realm).await {
+        Ok(page) => Json(ApiResponse::success(page)).into_response(),
+        Err(response) => response,
+    }
+}
+
+/// Remote phonebook for Yealink phones
+pub async fn yealink_directory(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Query(params): Query<DirectoryParams>,
+) -> Response {
+    phone_directory(&state, &headers, &params, render_yealink).await
+}
+
+/// Remote phonebook for Poly phones
+pub async fn poly_directory(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Query(params): Query<DirectoryParams>,
+) -> Response {
+    phone_directory(&state, &headers, &params, render_poly).await
+}
+
+async fn phone_directory(
+    state: &AppState,
+    headers: &HeaderMap,
+    params: &DirectoryParams,
+    render: fn(&[DirectoryEntry]) -> String,
+) -> Response {
+    let device = match request_token(headers, params.token.as_deref()) {
+        Some(token) => match authenticate_device(state, &token) {
+            Ok(device) => device,
+            Err(response) => return response,
+        },
+        None => return (StatusCode::UNAUTHORIZED, "Device token required").into_response(),
+    };
+    info!("API: Directory lookup by device {}", device.device_id);
+
+    match lookup(state, params, Some(device.realm)).await {
+        Ok(page) => (
+            [(header::CONTENT_TYPE, "application/xml; charset=utf-8")],
+            render(&page.entries),
+        )
+            .into_response(),
+        Err(response) => response,
+    }
+}
+
+/// Device token from the `token` parameter or a bearer `Authorization`
+pub(super) fn request_token(headers: &HeaderMap, token: Option<&str>) -> Option<String> {
+    token.map(str::to_string).or_else(|| {
+        headers
+            .get(header::AUTHORIZATION)?
+            .to_str()
+            .ok()?
+            .strip_prefix("Bearer ")
+            .map(|token| token.trim().to_string())
+    })
+}
+
+#[allow(clippy::result_large_err)]
+pub(super) fn authenticate_device(state: &AppState, token: &str) -> Result<DeviceIdentity, Response> {
+    let device = state
+        .device_tokens
+        .as_ref()
+        .and_then(|tokens| tokens.verify(token));
+    device.ok_or_else(|| {
+        warn!("API: Device request with unknown device token");
+        (
+            StatusCode::UNAUTHORIZED,
+            Json(ApiResponse::<()>::error("Invalid device token".to_string())),
+        )
+            .into_response()
+    })
+}
+
+/// Run the search and attach presence
+async fn lookup(
+    state: &AppState,
+    params: &DirectoryParams,
+    realm: Option<String>,
+) -> Result<DirectoryPage, Response> {
+    let query = DirectoryQuery::new(params.q.as_str(), realm, params.limit, params.offset);
+
+    // One extra row tells whether another page follows
+    let mut probe = query.clone();
+    probe.limit += 1;
+    let mut users = state.user_repository.search(&probe).await.map_err(|e| {
+        error!("API: Directory search failed: {}", e);
+        StatusCode::INTERNAL_SERVER_ERROR.into_response()
+    })?;
+    let next_offset = if users.len() as i64 > query.limit {
+        users.truncate(query.limit as usize);
+        Some(query.offset + query.limit)
+    } else {
+        None
+    };
+
+    let mut entries = Vec::with_capacity(users.len());
+    for user in &users {
+        let presence = match &state.registrar {
+            Some(registrar) if registrar.is_registered(&user.sip_uri()).await => {
+                Some("online".to_string())
+            }
+            Some(_) => Some("offline".to_string()),
+            None => None,
+        };
+        entries.push(directory_entry(user, presence));
+    }
+
+    Ok(DirectoryPage {
+        entries,
+        limit: query.limit,
+        offset: query.offset,
+        next_offset,
+    })
+}
+
+fn directory_entry(user: &User, presence: Option<String>) -> DirectoryEntry {
+    DirectoryEntry {
+        display_name: DirectoryQuery::entry_name(user).to_string(),
+        extension: user.username.clone(),
+        department: user.department.clone(),
+        presence,
+    }
+}
+
+/// Escape text for XML element content and attribute values
+pub fn xml_escape(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for c in value.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&apos;"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// `YealinkIPPhoneDirectory` document
+pub fn render_yealink(entries: &[DirectoryEntry]) -> String {
+    let mut xml =
+        String::from("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n<YealinkIPPhoneDirectory>\n");
+    for entry in entries {
+        xml.push_str(&format!(
+            "  <DirectoryEntry>\n    <Name>{}</Name>\n    <Telephone>{}</Telephone>\n  </DirectoryEntry>\n",
+            xml_escape(&entry.display_name),
+            xml_escape(&entry.extension)
+        ));
+    }
+    xml.push_str("</YealinkIPPhoneDirectory>\n");
+    xml
+}
+
+/// Poly (Polycom) `directory` document; the display name is split into
+/// first and last name at the last space
+pub fn render_poly(entries: &[DirectoryEntry]) -> String {
+    let mut xml =
+        String::from("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n<directory>\n  <item_list>\n");
+    for entry in entries {
+        let name = entry.display_name.trim();
+        let (first, last) = name.rsplit_once(' ').unwrap_or(("", name));
+        xml.push_str(&format!(
+            "    <item>\n      <fn>{}</fn>\n      <ln>{}</ln>\n      <ct>{}</ct>\n    </item>\n",
+            xml_escape(first.trim()),
+            xml_escape(last),
+            xml_escape(&entry.extension)
+        ));
+    }
+    xml.push_str("  </item_list>\n</directory>\n");
+    xml
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(display_name: &str, extension: &str) -> DirectoryEntry {
+        DirectoryEntry {
+            display_name: display_name.to_string(),
+            extension: extension.to_string(),
+            department: None,
+            presence: None,
+        }
+    }
+
+    #[test]
+    fn test_yealink_escapes_names() {
+        let xml = render_yealink(&[entry("Smith & Wesson <Sales>", "1001")]);
+        assert!(xml.contains("<Name>Smith &amp; Wesson &lt;Sales&gt;</Name>"));
+        assert!(xml.contains("<Telephone>1001</Telephone>"));
+        assert!(!xml.contains("& "));
+        assert!(xml.starts_with("<?xml"));
+    }
+
+    #[test]
+    fn test_poly_escapes_and_splits_names() {
+        let xml = render_poly(&[entry("AT&T Help Desk", "1002"), entry("Reception", "100")]);
+        assert!(xml.contains("<fn>AT&amp;T Help</fn>"));
+        assert!(xml.contains("<ln>Desk</ln>"));
+        assert!(xml.contains("<fn></fn>\n      <ln>Reception</ln>"));
+        assert!(!xml.contains("AT&T"));
+        assert_eq!(
+            xml_escape("O'Brien \"Bob\""),
+            "O&apos;Brien &quot;Bob&quot;"
+        );
+    }
+}
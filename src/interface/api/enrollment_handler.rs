@@ -0,0 +1,176 @@
+//! Softphone enrollment API handlers
+//!
+//! `POST /api/users/:id/enrollments` creates an enrollment link (and QR
+//! payload) for a user; the softphone opens `GET /enroll/:token`, which
+//! needs no credentials, is rate limited per client address (the peer, or
+//! the `X-Forwarded-For` of a trusted proxy) and answers with the connection
+//! details and a password of the new device. The devices of a user are
+//! listed and revoked under `/api/users/:id/devices`.
+//! Managing enrollments takes HTTP Basic credentials of the user or of an
+//! admin with `user:update`.
+
+use super::cdr_dto::ApiResponse;
+use super::diagnostics_handler::authenticate;
+use super::user_handler::AppState;
+use crate::domain::device_enrollment::{CreateEnrollment, EnrollmentError, EnrollmentService};
+use crate::domain::user::Permission;
+use axum::{
+    extract::{ConnectInfo, Path, State},
+    http::{header, HeaderMap, StatusCode},
+    response::{IntoResponse, Response},
+    Json,
+};
+use std::net::SocketAddr;
+use std::sync::Arc;
+use tracing::{error, info, warn};
+use uuid::Uuid;
+
+fn service_unavailable() -> Response {
+    error!("Enrollment service not available");
+    (
+        StatusCode::SERVICE_UNAVAILABLE,
+        Json(ApiResponse::<()>::error(
+            "Softphone enrollment not available".to_string(),
+        )),
+    )
+        .into_response()
+}
+
+#[allow(clippy::result_large_err)]
+fn service(state: &AppState) -> Result<&Arc<EnrollmentService>, Response> {
+    state.enrollment.as_ref().ok_or_else(service_unavailable)
+}
+
+fn error_response(e: EnrollmentError) -> Response {
+    let status = match e {
+        EnrollmentError::Invalid(_) => StatusCode::BAD_REQUEST,
+        EnrollmentError::UserNotFound(_)
+        | EnrollmentError::DeviceNotFound(_)
+        | EnrollmentError::InvalidToken => StatusCode::NOT_FOUND,
+        EnrollmentError::RateLimited => StatusCode::TOO_MANY_REQUESTS,
+        EnrollmentError::Repository(ref e) => {
+            error!("API: Enrollment database error: {}", e);
+            return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+        }
+    };
+    (status, Json(ApiResponse::<()>::error(e.to_string()))).into_response()
+}
+
+/// Check the caller is the user or may update users
+pub(super) async fn authorize_for_user(state: &AppState, headers: &HeaderMap, id: i32) -> Result<(), Response> {
+    let Some(diagnostics) = state.diagnostics.clone() else {
+        error!("Admin authentication not available");
+        return Err((
+            StatusCode::SERVICE_UNAVAILABLE,
+            Json(ApiResponse::<()>::error(
+                "Admin authentication not available".to_string(),
+            )),
+        )
+            .into_response());
+    };
+
+    let (user, permissions) = authenticate(state, &diagnostics, headers).await?;
+    if user.id != id && !permissions.contains(&Permission::UserUpdate) {
+        warn!("API: {} denied access to the devices of user {}", user.username, id);
+        return Err((
+            StatusCode::FORBIDDEN,
+            Json(ApiResponse::<()>::error(format!(
+                "Permission {} required",
+                Permission::UserUpdate.as_str()
+            ))),
+        )
+            .into_response());
+    }
+    Ok(())
+}
+
+/// Create an enrollment link for a user
+pub async fn create_enrollment(
+    State(state): State<AppState>,
+    Path(id): Path<i32>,
+    headers: HeaderMap,
+    Json(request): Json<CreateEnrollment>,
+) -> Response {
+    let service = match service(&state) {
+        Ok(service) => service,
+        Err(response) => return response,
+    };
+    if let Err(response) = authorize_for_user(&state, &headers, id).await {
+        return response;
+    }
+
+    match service.create(id, request).await {
+        Ok(issued) => (StatusCode::CREATED, Json(ApiResponse::success(issued))).into_response(),
+        Err(e) => error_response(e),
+    }
+}
+
+/// Redeem an enrollment token: the connection details and the password of
+/// a new device
+pub async fn redeem_enrollment(
+    State(state): State<AppState>,
+    Path(token): Path<String>,
+    peer: Option<ConnectInfo<SocketAddr>>,
+    headers: HeaderMap,
+) -> Response {
+    let service = match service(&state) {
+        Ok(service) => service,
+        Err(response) => return response,
+    };
+
+    let client = service.client_address(
+        peer.map(|ConnectInfo(peer)| peer.ip()),
+        headers.get("x-forwarded-for").and_then(|v| v.to_str().ok()),
+    );
+    match service.redeem(&token, &client).await {
+        Ok(bundle) => {
+            info!("API: Enrolled device {} of {}", bundle.device_id, bundle.username);
+            (
+                [(header::CACHE_CONTROL, "no-store")],
+                Json(ApiResponse::success(bundle)),
+            )
+                .into_response()
+        }
+        Err(e) => error_response(e),
+    }
+}
+
+/// Enrolled devices of a user, revoked ones included
+pub async fn list_user_devices(
+    State(state): State<AppState>,
+    Path(id): Path<i32>,
+    headers: HeaderMap,
+) -> Response {
+    let service = match service(&state) {
+        Ok(service) => service,
+        Err(response) => return response,
+    };
+    if let Err(response) = authorize_for_user(&state, &headers, id).await {
+        return response;
+    }
+
+    match service.devices(id).await {
+        Ok(devices) => Json(ApiResponse::success(devices)).into_response(),
+        Err(e) => error_response(e),
+    }
+}
+
+/// Revoke an enrolled device; it can no longer register
+pub async fn revoke_user_device(
+    State(state): State<AppState>,
+    Path((id, device_id)): Path<(i32, Uuid)>,
+    headers: HeaderMap,
+) -> Response {
+    let service = match service(&state) {
+        Ok(service) => service,
+        Err(response) => return response,
+    };
+    if let Err(response) = authorize_for_user(&state, &headers, id).await {
+        return response;
+    }
+
+    match service.revoke(id, device_id).await {
+        Ok(()) => StatusCode::NO_CONTENT.into_response(),
+        Err(e) => error_response(e),
+    }
+}
@@ -0,0 +1,75 @@
+//! Feature code API handlers
+//!
+//! `GET /api/feature-codes` lists the star codes in effect, for provisioning
+//! templates and user documentation: the defaults, then every tenant with
+//! its own overrides. `?realm=` lists a single tenant's codes.
+
+use super::cdr_dto::ApiResponse;
+use super::user_handler::AppState;
+use crate::domain::feature_code::EffectiveFeatureCode;
+use axum::{
+    extract::{Query, State},
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    Json,
+};
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Deserialize)]
+pub struct FeatureCodesQuery {
+    pub realm: Option<String>,
+}
+
+/// Codes of one tenant
+#[derive(Debug, Serialize)]
+pub struct TenantFeatureCodes {
+    /// `None` for tenants without their own overrides
+    pub realm: Option<String>,
+    pub codes: Vec<EffectiveFeatureCode>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct FeatureCodesResponse {
+    /// Prompt for unknown star codes; `None` answers them 404
+    pub unknown_code_announcement: Option<String>,
+    pub tenants: Vec<TenantFeatureCodes>,
+}
+
+/// List the effective feature codes per tenant
+pub async fn list_feature_codes(
+    State(state): State<AppState>,
+    Query(query): Query<FeatureCodesQuery>,
+) -> Response {
+    let registry = match &state.feature_codes {
+        Some(registry) => registry,
+        None => {
+            return (
+                StatusCode::SERVICE_UNAVAILABLE,
+                Json(ApiResponse::<()>::error(
+                    "Feature codes not available".to_string(),
+                )),
+            )
+                .into_response()
+        }
+    };
+
+    let realms = match query.realm {
+        Some(realm) => vec![Some(realm)],
+        None => std::iter::once(None)
+            .chain(registry.tenants().into_iter().map(Some))
+            .collect(),
+    };
+    let tenants = realms
+        .into_iter()
+        .map(|realm| TenantFeatureCodes {
+            codes: registry.effective_codes(realm.as_deref()),
+            realm,
+        })
+        .collect();
+
+    Json(ApiResponse::success(FeatureCodesResponse {
+        unknown_code_announcement: registry.unknown_code_announcement().map(str::to_string),
+        tenants,
+    }))
+    .into_response()
+}
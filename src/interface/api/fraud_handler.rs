@@ -0,0 +1,84 @@
+//! Fraud detection API handlers
+
+use super::cdr_dto::ApiResponse;
+use super::user_handler::AppState;
+use crate::domain::fraud_detection::FraudStatus;
+use crate::domain::user::User;
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    Json,
+};
+use serde::{Deserialize, Serialize};
+use tracing::{error, info};
+
+/// Clear suspension response
+#[derive(Debug, Serialize, Deserialize)]
+pub struct FraudClearResponse {
+    pub username: String,
+    /// False when the user was not suspended
+    pub cleared: bool,
+}
+
+fn detector_unavailable() -> Response {
+    error!("Fraud detector not available");
+    (
+        StatusCode::SERVICE_UNAVAILABLE,
+        Json(ApiResponse::<()>::error("Fraud detection not enabled".to_string())),
+    )
+        .into_response()
+}
+
+async fn find_user(state: &AppState, id: i32) -> Result<User, Response> {
+    match state.user_repository.find_by_id(id).await {
+        Ok(Some(user)) => Ok(user),
+        Ok(None) => Err((
+            StatusCode::NOT_FOUND,
+            Json(ApiResponse::<()>::error(format!("User {} not found", id))),
+        )
+            .into_response()),
+        Err(e) => {
+            error!("API: Failed to get user: {}", e);
+            Err(StatusCode::INTERNAL_SERVER_ERROR.into_response())
+        }
+    }
+}
+
+/// Current fraud counters and suspension state of a user
+pub async fn get_fraud_status(State(state): State<AppState>, Path(id): Path<i32>) -> Response {
+    let fraud = match &state.fraud_detector {
+        Some(fraud) => fraud,
+        None => return detector_unavailable(),
+    };
+    let user = match find_user(&state, id).await {
+        Ok(user) => user,
+        Err(response) => return response,
+    };
+
+    let status: FraudStatus = fraud.status(&user.username);
+    Json(ApiResponse::success(status)).into_response()
+}
+
+/// Re-enable outbound calling for a suspended user
+pub async fn clear_fraud_suspension(
+    State(state): State<AppState>,
+    Path(id): Path<i32>,
+) -> Response {
+    let fraud = match &state.fraud_detector {
+        Some(fraud) => fraud,
+        None => return detector_unavailable(),
+    };
+    let user = match find_user(&state, id).await {
+        Ok(user) => user,
+        Err(response) => return response,
+    };
+
+    info!("API: Clearing fraud suspension for user {}", user.username);
+    let cleared = fraud.clear(&user.username, "api");
+    Json(ApiResponse::success(FraudClearResponse {
+        username: user.username,
+        cleared,
+    }))
+    .into_response()
+}
@@ -0,0 +1,108 @@
+//! Header rules API handlers
+//!
+//! `POST /api/admin/header-rules/preview` shows the headers a sample
+//! request ends up with after the configured trunk and route rules, so
+//! rules can be checked before a real call uses them. Credentials are
+//! checked as for diagnostics.
+
+use super::cdr_dto::ApiResponse;
+use super::diagnostics_handler::{authorize, client_ip};
+use super::user_handler::AppState;
+use crate::infrastructure::protocols::sip::{HeaderContext, HeaderDirection};
+use axum::{
+    extract::State,
+    http::{HeaderMap, StatusCode},
+    response::{IntoResponse, Response},
+    Json,
+};
+use serde::{Deserialize, Serialize};
+use tracing::error;
+
+/// One SIP header
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct HeaderDto {
+    pub name: String,
+    pub value: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct HeaderPreviewRequest {
+    pub direction: HeaderDirection,
+    /// Trunk host the request comes from (inbound) or target URI (outbound)
+    pub peer: String,
+    /// Request-URI of the sample request; defaults to `peer` for outbound
+    /// requests
+    #[serde(default)]
+    pub request_uri: Option<String>,
+    /// Headers of the sample request
+    #[serde(default)]
+    pub headers: Vec<HeaderDto>,
+    /// Values of the template variables
+    #[serde(default)]
+    pub context: HeaderContext,
+}
+
+#[derive(Debug, Serialize)]
+pub struct HeaderPreviewResponse {
+    pub headers: Vec<HeaderDto>,
+}
+
+fn unavailable(what: &str) -> Response {
+    error!("{} not available", what);
+    (
+        StatusCode::SERVICE_UNAVAILABLE,
+        Json(ApiResponse::<()>::error(format!("{} not available", what))),
+    )
+        .into_response()
+}
+
+/// Headers of a sample request after the header rules (requires
+/// `system:config`)
+pub async fn preview_header_rules(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Json(request): Json<HeaderPreviewRequest>,
+) -> Response {
+    let Some(diagnostics) = state.diagnostics.clone() else {
+        return unavailable("Admin authentication");
+    };
+    let Some(header_rules) = state.header_rules.clone() else {
+        return unavailable("Header rules");
+    };
+
+    let ip = client_ip(&headers);
+    if let Err(response) = authorize(
+        &state,
+        &diagnostics,
+        &headers,
+        &ip,
+        "header_rules",
+        "preview",
+    )
+    .await
+    {
+        return response;
+    }
+
+    let request_uri = request.request_uri.as_deref().unwrap_or(&request.peer);
+    let sample: Vec<(String, String)> = request
+        .headers
+        .into_iter()
+        .map(|h| (h.name, h.value))
+        .collect();
+    let result = header_rules.preview(
+        request.direction,
+        &request.peer,
+        request_uri,
+        &sample,
+        &request.context,
+    );
+
+    Json(ApiResponse::success(HeaderPreviewResponse {
+        headers: result
+            .into_iter()
+            .map(|(name, value)| HeaderDto { name, value })
+            .collect(),
+    }))
+    .into_response()
+}
@@ -0,0 +1,391 @@
+//! `Idempotency-Key` handling of mutating requests
+//!
+//! Call-control and provisioning routes run behind [`idempotency_guard`]:
+//! a POST, PUT, PATCH or DELETE with an `Idempotency-Key` header runs once,
+//! and repeats of it get the first response back with
+//! `Idempotent-Replayed: true`. The key on a different request is refused
+//! with 422; a repeat while the first request is still running waits for
+//! it, then gets 409. Handlers find the key as an [`Idempotency`] request
+//! extension.
+//!
+//! Keys are kept per caller: the verified user or device, another
+//! `Authorization` header by its hash, anonymous requests by peer address.
+//! A request whose credentials do not verify runs without its key, so a
+//! replay never skips the handler's authentication.
+
+use super::cdr_dto::ApiResponse;
+use super::diagnostics_handler::basic_credentials;
+use super::directory_handler::request_token;
+use super::user_handler::AppState;
+use crate::infrastructure::idempotency::{Begin, Idempotency, StoredResponse};
+use axum::{
+    body::{to_bytes, Body},
+    extract::{ConnectInfo, Query, Request, State},
+    http::{header, HeaderMap, HeaderValue, Method, StatusCode, Uri},
+    middleware::Next,
+    response::{IntoResponse, Response},
+    Json,
+};
+use metrics::counter;
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use tracing::{error, warn};
+
+/// Request header carrying the key
+pub const IDEMPOTENCY_KEY: &str = "idempotency-key";
+/// Response header marking a replay
+pub const IDEMPOTENT_REPLAYED: &str = "idempotent-replayed";
+
+/// Largest request or response body a key is kept for
+const MAX_BODY_BYTES: usize = 2 * 1024 * 1024;
+
+/// Path prefixes of the route groups behind the guard (see `router.rs`)
+const GUARDED_PATHS: &[&str] = &[
+    "/users",
+    "/speed-dials",
+    "/calls",
+    "/conferences",
+    "/api/calls/",
+    "/api/devices/",
+    "/api/trunks/",
+    "/api/tenants/",
+    "/api/campaigns",
+];
+
+/// Whether a mutating request to `path` may carry an `Idempotency-Key`
+pub(super) fn guarded(path: &str) -> bool {
+    GUARDED_PATHS.iter().any(|prefix| path.starts_with(prefix))
+}
+
+fn refused(status: StatusCode, message: &str) -> Response {
+    (status, Json(ApiResponse::<()>::error(message.to_string()))).into_response()
+}
+
+/// Hash telling a repeat of a request from another request with its key
+fn fingerprint(method: &Method, uri: &str, body: &[u8]) -> String {
+    let mut hasher = Sha256::new();
+    hasher.update(method.as_str().as_bytes());
+    hasher.update(b"\n");
+    hasher.update(uri.as_bytes());
+    hasher.update(b"\n");
+    hasher.update(body);
+    hex::encode(hasher.finalize())
+}
+
+/// Who sent the request, or `None` when its credentials do not verify
+async fn caller(
+    state: &AppState,
+    uri: &Uri,
+    headers: &HeaderMap,
+    peer: Option<SocketAddr>,
+) -> Option<String> {
+    if let Some((username, password)) = basic_credentials(headers) {
+        return match state.user_repository.verify_credentials(&username, &password).await {
+            Ok(Some(user)) => Some(format!("user:{}", user.id)),
+            Ok(None) => None,
+            Err(e) => {
+                error!("API: Failed to verify credentials of an idempotent request: {}", e);
+                None
+            }
+        };
+    }
+    let query = Query::<HashMap<String, String>>::try_from_uri(uri).ok();
+    let token = query.as_ref().and_then(|Query(query)| query.get("token"));
+    if let Some(token) = request_token(headers, token.map(String::as_str)) {
+        let device = state
+            .device_tokens
+            .as_ref()
+            .and_then(|tokens| tokens.verify(&token))?;
+        return Some(format!("device:{}@{}", device.device_id, device.realm));
+    }
+    if let Some(authorization) = headers.get(header::AUTHORIZATION) {
+        return Some(format!("auth:{}", hex::encode(Sha256::digest(authorization.as_bytes()))));
+    }
+    Some(match peer {
+        Some(peer) => format!("peer:{}", peer.ip()),
+        None => "anonymous".to_string(),
+    })
+}
+
+/// Key of `caller`'s `key` in the cache, the same length whatever the two are
+fn scoped_key(caller: &str, key: &str) -> String {
+    let mut hasher = Sha256::new();
+    hasher.update(caller.as_bytes());
+    hasher.update(b"\n");
+    hasher.update(key.as_bytes());
+    hex::encode(hasher.finalize())
+}
+
+fn replay(stored: StoredResponse) -> Response {
+    let status = StatusCode::from_u16(stored.status).unwrap_or(StatusCode::OK);
+    let mut response = (status, stored.body).into_response();
+    let headers = response.headers_mut();
+    if let Some(content_type) = stored
+        .content_type
+        .and_then(|value| HeaderValue::from_str(&value).ok())
+    {
+        headers.insert(header::CONTENT_TYPE, content_type);
+    }
+    headers.insert(IDEMPOTENT_REPLAYED, HeaderValue::from_static("true"));
+    response
+}
+
+/// Run a mutating request with an `Idempotency-Key` at most once
+pub async fn idempotency_guard(
+    State(state): State<AppState>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let Some(cache) = state.idempotency.clone() else {
+        return next.run(request).await;
+    };
+    if matches!(*request.method(), Method::GET | Method::HEAD | Method::OPTIONS) {
+        return next.run(request).await;
+    }
+    let Some(key) = request.headers().get(IDEMPOTENCY_KEY) else {
+        return next.run(request).await;
+    };
+    let key = match key.to_str() {
+        Ok(key) if !key.is_empty() && key.len() <= 255 => key.to_string(),
+        _ => {
+            return refused(
+                StatusCode::BAD_REQUEST,
+                "Idempotency-Key must be 1 to 255 visible ASCII characters",
+            )
+        }
+    };
+
+    let peer = request
+        .extensions()
+        .get::<ConnectInfo<SocketAddr>>()
+        .map(|ConnectInfo(peer)| *peer);
+    let Some(caller) = caller(&state, request.uri(), request.headers(), peer).await else {
+        return next.run(request).await;
+    };
+    let key = scoped_key(&caller, &key);
+
+    let (parts, body) = request.into_parts();
+    let body = match to_bytes(body, MAX_BODY_BYTES).await {
+        Ok(body) => body,
+        Err(_) => return refused(StatusCode::PAYLOAD_TOO_LARGE, "Request body too large"),
+    };
+    let uri = parts.uri.path_and_query().map(|p| p.as_str()).unwrap_or("/");
+    let fingerprint = fingerprint(&parts.method, uri, &body);
+
+    match cache.begin(&key, &fingerprint).await {
+        Begin::Execute => {}
+        Begin::Replay(stored) => {
+            counter!("api_idempotent_requests_total", "outcome" => "replayed").increment(1);
+            return replay(stored);
+        }
+        Begin::Mismatch => {
+            counter!("api_idempotent_requests_total", "outcome" => "mismatch").increment(1);
+            warn!("API: Idempotency key reused for {} {}", parts.method, uri);
+            return refused(
+                StatusCode::UNPROCESSABLE_ENTITY,
+                "Idempotency-Key was used with a different request",
+            );
+        }
+        Begin::InProgress => {
+            counter!("api_idempotent_requests_total", "outcome" => "in_progress").increment(1);
+            return (
+                StatusCode::CONFLICT,
+                [(header::RETRY_AFTER, "1")],
+                Json(ApiResponse::<()>::error(
+                    "A request with this Idempotency-Key is still running".to_string(),
+                )),
+            )
+                .into_response();
+        }
+    }
+    counter!("api_idempotent_requests_total", "outcome" => "executed").increment(1);
+
+    let mut request = Request::from_parts(parts, Body::from(body));
+    request
+        .extensions_mut()
+        .insert(Idempotency::new(key.clone(), cache.clone()));
+    let response = next.run(request).await;
+    if response.status().is_server_error() {
+        cache.abandon(&key);
+        return response;
+    }
+
+    let (parts, body) = response.into_parts();
+    let body = match to_bytes(body, MAX_BODY_BYTES).await {
+        Ok(body) => body,
+        Err(e) => {
+            error!("API: Failed to read response to keep for replay: {}", e);
+            cache.abandon(&key);
+            return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+        }
+    };
+    let content_type = parts
+        .headers
+        .get(header::CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .map(str::to_string);
+    cache
+        .complete(&key, &fingerprint, parts.status.as_u16(), content_type, body.to_vec())
+        .await;
+    Response::from_parts(parts, Body::from(body))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::domain::user::repository::MockUserRepository;
+    use crate::domain::user::User;
+    use crate::infrastructure::idempotency::{IdempotencyCache, IdempotencyConfig};
+    use crate::test_support::ManualClock;
+    use axum::{extract::Extension, middleware, routing::post, Router};
+    use chrono::Utc;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+    use tower::ServiceExt;
+
+    static TRANSFERS: AtomicUsize = AtomicUsize::new(0);
+
+    async fn transfer(idempotency: Option<Extension<Idempotency>>, body: String) -> Response {
+        let first = match idempotency {
+            Some(Extension(idempotency)) => idempotency.first("refer"),
+            None => true,
+        };
+        if first {
+            TRANSFERS.fetch_add(1, Ordering::SeqCst);
+        }
+        tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+        (StatusCode::CREATED, format!("transferred to {}", body)).into_response()
+    }
+
+    static ORDERS: AtomicUsize = AtomicUsize::new(0);
+
+    /// Answers with the caller's name, so a replay to another caller shows
+    async fn order(headers: HeaderMap) -> Response {
+        ORDERS.fetch_add(1, Ordering::SeqCst);
+        let (username, _) = basic_credentials(&headers).unwrap_or_default();
+        (StatusCode::CREATED, format!("order of {}", username)).into_response()
+    }
+
+    fn user(id: i32, username: &str) -> User {
+        User {
+            id,
+            username: username.to_string(),
+            password_hash: String::new(),
+            sip_ha1: None,
+            realm: "example.com".to_string(),
+            display_name: None,
+            email: None,
+            department: None,
+            locale: None,
+            timezone: None,
+            enabled: true,
+            role_id: None,
+            created_at: Utc::now(),
+            updated_at: Utc::now(),
+        }
+    }
+
+    fn basic(username: &str, password: &str) -> String {
+        use base64::Engine;
+        let encoded = base64::engine::general_purpose::STANDARD
+            .encode(format!("{}:{}", username, password));
+        format!("Basic {}", encoded)
+    }
+
+    fn request(key: &str, body: &str) -> Request {
+        Request::builder()
+            .method(Method::POST)
+            .uri("/calls/c1/transfer")
+            .header(IDEMPOTENCY_KEY, key)
+            .body(Body::from(body.to_string()))
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_retries_replay_the_first_response() {
+        let clock = Arc::new(ManualClock::new());
+        let cache = IdempotencyCache::new(IdempotencyConfig {
+            ttl_secs: 60,
+            wait_ms: 1_000,
+            ..Default::default()
+        })
+        .with_clock(clock.clone());
+        let mut state = AppState::for_tests(Arc::new(MockUserRepository::new()));
+        state.idempotency = Some(Arc::new(cache));
+        let app = Router::new()
+            .route("/calls/:call_id/transfer", post(transfer))
+            .layer(middleware::from_fn_with_state(state.clone(), idempotency_guard))
+            .with_state(state);
+
+        // Concurrent duplicates run once
+        let (first, second) = tokio::join!(
+            app.clone().oneshot(request("k1", "bob")),
+            app.clone().oneshot(request("k1", "bob")),
+        );
+        let (first, second) = (first.unwrap(), second.unwrap());
+        assert_eq!(TRANSFERS.load(Ordering::SeqCst), 1);
+        assert_eq!(first.status(), StatusCode::CREATED);
+        assert_eq!(second.status(), StatusCode::CREATED);
+        assert!(second.headers().contains_key(IDEMPOTENT_REPLAYED));
+        let body = to_bytes(second.into_body(), usize::MAX).await.unwrap();
+        assert_eq!(&body[..], b"transferred to bob");
+
+        let response = app.clone().oneshot(request("k1", "carol")).await.unwrap();
+        assert_eq!(response.status(), StatusCode::UNPROCESSABLE_ENTITY);
+        assert_eq!(TRANSFERS.load(Ordering::SeqCst), 1);
+
+        // After the TTL the key is new again
+        clock.advance(std::time::Duration::from_secs(61));
+        let response = app.oneshot(request("k1", "carol")).await.unwrap();
+        assert_eq!(response.status(), StatusCode::CREATED);
+        assert!(!response.headers().contains_key(IDEMPOTENT_REPLAYED));
+        assert_eq!(TRANSFERS.load(Ordering::SeqCst), 2);
+    }
+
+    #[tokio::test]
+    async fn test_keys_are_kept_per_caller() {
+        let mut users = MockUserRepository::new();
+        users.expect_verify_credentials().returning(|username, password| {
+            let id = match (username, password) {
+                ("alice", "secret") => 1,
+                ("bob", "secret") => 2,
+                _ => return Ok(None),
+            };
+            Ok(Some(user(id, username)))
+        });
+        let mut state = AppState::for_tests(Arc::new(users));
+        state.idempotency = Some(Arc::new(IdempotencyCache::new(IdempotencyConfig::default())));
+        let app = Router::new()
+            .route("/calls", post(order))
+            .layer(middleware::from_fn_with_state(state.clone(), idempotency_guard))
+            .with_state(state);
+        let send = |authorization: String| {
+            let request = Request::builder()
+                .method(Method::POST)
+                .uri("/calls")
+                .header(IDEMPOTENCY_KEY, "same-key")
+                .header(header::AUTHORIZATION, authorization)
+                .body(Body::from("{}"))
+                .unwrap();
+            app.clone().oneshot(request)
+        };
+
+        let alice = send(basic("alice", "secret")).await.unwrap();
+        let bob = send(basic("bob", "secret")).await.unwrap();
+        assert!(!bob.headers().contains_key(IDEMPOTENT_REPLAYED));
+        let alice = to_bytes(alice.into_body(), usize::MAX).await.unwrap();
+        let bob = to_bytes(bob.into_body(), usize::MAX).await.unwrap();
+        assert_eq!(&alice[..], b"order of alice");
+        assert_eq!(&bob[..], b"order of bob");
+
+        // Wrong password: no replay of alice's response, the handler runs
+        let guess = send(basic("alice", "guess")).await.unwrap();
+        assert!(!guess.headers().contains_key(IDEMPOTENT_REPLAYED));
+
+        // Alice's own retry is replayed
+        let retry = send(basic("alice", "secret")).await.unwrap();
+        assert!(retry.headers().contains_key(IDEMPOTENT_REPLAYED));
+        assert_eq!(ORDERS.load(Ordering::SeqCst), 3);
+    }
+}
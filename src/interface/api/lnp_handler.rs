@@ -0,0 +1,109 @@
+//! Number portability cache API handlers
+//!
+//! `GET /api/routing/lnp/{number}` shows the cached lookup answer of a
+//! number with the cache statistics; `DELETE` flushes it so the next call
+//! asks the service again. Numbers are normalized as dialed in `tenant`
+//! (query parameter) or the default country. Credentials are checked as for
+//! diagnostics.
+
+use super::cdr_dto::ApiResponse;
+use super::diagnostics_handler::{authorize, client_ip};
+use super::user_handler::AppState;
+use crate::infrastructure::lnp::{CacheStats, CachedRoute, LnpResolver};
+use axum::{
+    extract::{Path, Query, State},
+    http::{HeaderMap, StatusCode},
+    response::{IntoResponse, Response},
+    Json,
+};
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+use tracing::{error, info};
+
+#[derive(Debug, Deserialize)]
+pub struct LnpQuery {
+    /// Tenant whose numbering plan normalizes the number
+    pub tenant: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct LnpEntryResponse {
+    pub entry: CachedRoute,
+    pub stats: CacheStats,
+}
+
+fn unavailable(what: &str) -> Response {
+    error!("{} not available", what);
+    (
+        StatusCode::SERVICE_UNAVAILABLE,
+        Json(ApiResponse::<()>::error(format!("{} not available", what))),
+    )
+        .into_response()
+}
+
+fn not_cached(number: &str) -> Response {
+    (
+        StatusCode::NOT_FOUND,
+        Json(ApiResponse::<()>::error(format!("{} is not cached", number))),
+    )
+        .into_response()
+}
+
+/// Number portability resolver, after checking credentials
+async fn resolver(
+    state: &AppState,
+    headers: &HeaderMap,
+    action: &str,
+) -> Result<Arc<LnpResolver>, Response> {
+    let Some(diagnostics) = state.diagnostics.clone() else {
+        return Err(unavailable("Admin authentication"));
+    };
+    let Some(lnp) = state.lnp.clone() else {
+        return Err(unavailable("Number portability"));
+    };
+    let ip = client_ip(headers);
+    authorize(state, &diagnostics, headers, &ip, "lnp", action).await?;
+    Ok(lnp)
+}
+
+/// Cached answer for a number (requires `system:config`)
+pub async fn get_lnp_entry(
+    State(state): State<AppState>,
+    Path(number): Path<String>,
+    Query(query): Query<LnpQuery>,
+    headers: HeaderMap,
+) -> Response {
+    let lnp = match resolver(&state, &headers, "get").await {
+        Ok(lnp) => lnp,
+        Err(response) => return response,
+    };
+
+    match lnp.cached(&number, query.tenant.as_deref()) {
+        Some(entry) => Json(ApiResponse::success(LnpEntryResponse {
+            entry,
+            stats: lnp.stats(),
+        }))
+        .into_response(),
+        None => not_cached(&number),
+    }
+}
+
+/// Flush the cached answer for a number (requires `system:config`)
+pub async fn flush_lnp_entry(
+    State(state): State<AppState>,
+    Path(number): Path<String>,
+    Query(query): Query<LnpQuery>,
+    headers: HeaderMap,
+) -> Response {
+    let lnp = match resolver(&state, &headers, "delete").await {
+        Ok(lnp) => lnp,
+        Err(response) => return response,
+    };
+
+    if lnp.flush(&number, query.tenant.as_deref()) {
+        info!("API: Flushed LNP cache entry of {}", number);
+        StatusCode::NO_CONTENT.into_response()
+    } else {
+        not_cached(&number)
+    }
+}
@@ -0,0 +1,186 @@
+//! Maintenance API handlers
+//!
+//! `POST /api/admin/maintenance/cdr-retention` runs CDR retention now,
+//! outside its schedule; with `dry_run` it only reports what would be
+//! anonymized and deleted. Credentials are checked as for diagnostics.
+//!
+//! `PUT /api/tenants/:id/maintenance` and `PUT /api/trunks/:id/maintenance`
+//! take a tenant (by realm) or a trunk (by name) out of service or put it
+//! back; `GET` on them, and `GET /api/maintenance` for every scope, show
+//! the state.
+
+use super::cdr_dto::ApiResponse;
+use super::diagnostics_handler::{authorize, client_ip};
+use super::user_handler::AppState;
+use crate::domain::security::{SecurityEvent, SecuritySeverity};
+use crate::infrastructure::protocols::sip::{
+    MaintenanceRegistry, MaintenanceRequest, MaintenanceScope, ScopeMaintenance,
+};
+use axum::{
+    extract::{Path, Query, State},
+    http::{HeaderMap, StatusCode},
+    response::{IntoResponse, Response},
+    Json,
+};
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+use tracing::{error, info, warn};
+
+#[derive(Debug, Default, Deserialize)]
+pub struct CdrRetentionQuery {
+    /// Count what would change without changing anything
+    #[serde(default)]
+    pub dry_run: bool,
+}
+
+fn unavailable(what: &str) -> Response {
+    error!("{} not available", what);
+    (
+        StatusCode::SERVICE_UNAVAILABLE,
+        Json(ApiResponse::<()>::error(format!("{} not available", what))),
+    )
+        .into_response()
+}
+
+/// Run CDR retention now (requires `system:config`)
+pub async fn run_cdr_retention(
+    State(state): State<AppState>,
+    Query(query): Query<CdrRetentionQuery>,
+    headers: HeaderMap,
+) -> Response {
+    let Some(diagnostics) = state.diagnostics.clone() else {
+        return unavailable("Admin authentication");
+    };
+    let Some(retention) = state.cdr_retention.clone() else {
+        return unavailable("CDR retention");
+    };
+
+    let ip = client_ip(&headers);
+    let action = if query.dry_run { "dry_run" } else { "run" };
+    let username =
+        match authorize(&state, &diagnostics, &headers, &ip, "cdr_retention", action).await {
+            Ok(username) => username,
+            Err(response) => return response,
+        };
+
+    info!("API: CDR retention {} requested by {}", action, username);
+    if !query.dry_run {
+        diagnostics.audit(
+            SecurityEvent::AdminAction {
+                admin_username: username,
+                ip,
+                action: action.to_string(),
+                target: "cdr_retention".to_string(),
+            },
+            SecuritySeverity::High,
+        );
+    }
+
+    match retention.run(query.dry_run).await {
+        Ok(report) => Json(ApiResponse::success(report)).into_response(),
+        Err(e) => {
+            error!("API: CDR retention failed: {}", e);
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(ApiResponse::<()>::error(e)),
+            )
+                .into_response()
+        }
+    }
+}
+
+/// Maintenance state of one tenant or trunk
+#[derive(Debug, Serialize)]
+pub struct MaintenanceStatus {
+    pub scope: MaintenanceScope,
+    pub enabled: bool,
+    pub maintenance: Option<ScopeMaintenance>,
+}
+
+#[allow(clippy::result_large_err)]
+fn maintenance(state: &AppState) -> Result<&Arc<MaintenanceRegistry>, Response> {
+    state
+        .maintenance
+        .as_ref()
+        .ok_or_else(|| unavailable("Maintenance mode"))
+}
+
+fn status(registry: &MaintenanceRegistry, scope: MaintenanceScope) -> MaintenanceStatus {
+    let maintenance = registry.get(&scope);
+    MaintenanceStatus {
+        scope,
+        enabled: maintenance.is_some(),
+        maintenance,
+    }
+}
+
+fn get_scope(state: &AppState, scope: MaintenanceScope) -> Response {
+    match maintenance(state) {
+        Ok(registry) => Json(ApiResponse::success(status(registry, scope))).into_response(),
+        Err(response) => response,
+    }
+}
+
+fn set_scope(state: &AppState, scope: MaintenanceScope, request: MaintenanceRequest) -> Response {
+    let registry = match maintenance(state) {
+        Ok(registry) => registry,
+        Err(response) => return response,
+    };
+
+    match registry.set(scope.clone(), request) {
+        Ok(_) => {
+            info!("API: Maintenance of {} updated", scope);
+            Json(ApiResponse::success(status(registry, scope))).into_response()
+        }
+        Err(e) => {
+            warn!("API: Maintenance of {} refused: {}", scope, e);
+            (
+                StatusCode::NOT_FOUND,
+                Json(ApiResponse::<()>::error(e.to_string())),
+            )
+                .into_response()
+        }
+    }
+}
+
+/// Every tenant and trunk in maintenance
+pub async fn list_maintenance(State(state): State<AppState>) -> Response {
+    match maintenance(&state) {
+        Ok(registry) => Json(ApiResponse::success(registry.list())).into_response(),
+        Err(response) => response,
+    }
+}
+
+/// Maintenance state of a tenant
+pub async fn get_tenant_maintenance(
+    State(state): State<AppState>,
+    Path(realm): Path<String>,
+) -> Response {
+    get_scope(&state, MaintenanceScope::Tenant(realm.to_ascii_lowercase()))
+}
+
+/// Put a tenant into maintenance or take it out
+pub async fn set_tenant_maintenance(
+    State(state): State<AppState>,
+    Path(realm): Path<String>,
+    Json(request): Json<MaintenanceRequest>,
+) -> Response {
+    set_scope(&state, MaintenanceScope::Tenant(realm.to_ascii_lowercase()), request)
+}
+
+/// Maintenance state of a trunk
+pub async fn get_trunk_maintenance(
+    State(state): State<AppState>,
+    Path(name): Path<String>,
+) -> Response {
+    get_scope(&state, MaintenanceScope::Trunk(name))
+}
+
+/// Put a trunk into maintenance or take it out
+pub async fn set_trunk_maintenance(
+    State(state): State<AppState>,
+    Path(name): Path<String>,
+    Json(request): Json<MaintenanceRequest>,
+) -> Response {
+    set_scope(&state, MaintenanceScope::Trunk(name), request)
+}
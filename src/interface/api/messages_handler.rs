@@ -0,0 +1,227 @@
+//! Instant message history API handlers
+//!
+//! History, unread counts and retention of the SIP MESSAGEs a user sent or
+//! received. Users are addressed by ID; messages are stored by username.
+
+use super::cdr_dto::ApiResponse;
+use super::user_handler::AppState;
+use crate::domain::instant_messaging::{InstantMessage, MessageFilter, MessageRepository};
+use crate::domain::user::User;
+use axum::{
+    extract::{Path, Query, State},
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    Json,
+};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::Arc;
+use tracing::{error, info};
+use uuid::Uuid;
+
+/// Query parameters for message history
+#[derive(Debug, Deserialize)]
+pub struct MessageHistoryQuery {
+    /// Only the conversation with this username
+    pub peer: Option<String>,
+    /// Only messages sent before this time; pass the oldest `sent_at` of a
+    /// page to get the next one
+    pub before: Option<DateTime<Utc>>,
+    #[serde(default = "default_limit")]
+    pub limit: usize,
+}
+
+fn default_limit() -> usize {
+    50
+}
+
+/// Maximum number of messages a single request may ask for
+const MAX_HISTORY_LIMIT: usize = 200;
+
+/// Query parameters selecting messages to delete
+#[derive(Debug, Deserialize)]
+pub struct DeleteMessagesQuery {
+    pub peer: Option<String>,
+    pub before: Option<DateTime<Utc>>,
+}
+
+/// Query parameters for mark-as-read
+#[derive(Debug, Deserialize)]
+pub struct MarkMessagesReadQuery {
+    pub peer: String,
+}
+
+/// A stored message
+#[derive(Debug, Serialize, Deserialize)]
+pub struct MessageDto {
+    pub id: Uuid,
+    pub from: String,
+    pub to: String,
+    pub content_type: String,
+    /// Body as UTF-8 (lossy for binary content)
+    pub content: String,
+    pub status: String,
+    pub sent_at: DateTime<Utc>,
+    pub delivered_at: Option<DateTime<Utc>>,
+    pub read_at: Option<DateTime<Utc>>,
+}
+
+impl From<InstantMessage> for MessageDto {
+    fn from(message: InstantMessage) -> Self {
+        Self {
+            id: message.id,
+            from: message.from,
+            to: message.to,
+            content_type: message.content_type.to_string(),
+            content: String::from_utf8_lossy(&message.content).into_owned(),
+            status: message.status.as_str().to_string(),
+            sent_at: message.timestamp,
+            delivered_at: message.delivered_at,
+            read_at: message.read_at,
+        }
+    }
+}
+
+/// Unread message counts
+#[derive(Debug, Serialize, Deserialize)]
+pub struct UnreadCountsResponse {
+    pub total: u64,
+    /// Unread messages per sender username
+    pub by_sender: HashMap<String, u64>,
+}
+
+/// Number of messages changed
+#[derive(Debug, Serialize, Deserialize)]
+pub struct MessagesAffectedResponse {
+    pub count: u64,
+}
+
+/// Message repository and the user addressed by `id`
+async fn repository_and_user(
+    state: &AppState,
+    id: i32,
+) -> Result<(Arc<dyn MessageRepository>, User), Response> {
+    let repository = match &state.message_repository {
+        Some(repository) => repository.clone(),
+        None => {
+            error!("Message repository not available");
+            return Err(StatusCode::SERVICE_UNAVAILABLE.into_response());
+        }
+    };
+
+    match state.user_repository.find_by_id(id).await {
+        Ok(Some(user)) => Ok((repository, user)),
+        Ok(None) => Err((
+            StatusCode::NOT_FOUND,
+            Json(ApiResponse::<()>::error(format!("User {} not found", id))),
+        )
+            .into_response()),
+        Err(e) => {
+            error!("API: Failed to get user: {}", e);
+            Err(StatusCode::INTERNAL_SERVER_ERROR.into_response())
+        }
+    }
+}
+
+/// Get a user's messages, newest first
+pub async fn get_message_history(
+    State(state): State<AppState>,
+    Path(id): Path<i32>,
+    Query(query): Query<MessageHistoryQuery>,
+) -> Response {
+    info!(
+        "API: Getting messages for user ID: {} (limit: {})",
+        id, query.limit
+    );
+
+    let (repository, user) = match repository_and_user(&state, id).await {
+        Ok(found) => found,
+        Err(response) => return response,
+    };
+
+    let filter = MessageFilter {
+        peer: query.peer,
+        before: query.before,
+    };
+    let limit = query.limit.clamp(1, MAX_HISTORY_LIMIT);
+
+    match repository.history(&user.username, &filter, limit).await {
+        Ok(messages) => {
+            let messages: Vec<MessageDto> = messages.into_iter().map(Into::into).collect();
+            Json(ApiResponse::success(messages)).into_response()
+        }
+        Err(e) => {
+            error!("API: Failed to list messages: {}", e);
+            StatusCode::INTERNAL_SERVER_ERROR.into_response()
+        }
+    }
+}
+
+/// Delete a user's messages (all, with a peer, or before a time)
+pub async fn delete_message_history(
+    State(state): State<AppState>,
+    Path(id): Path<i32>,
+    Query(query): Query<DeleteMessagesQuery>,
+) -> Response {
+    let (repository, user) = match repository_and_user(&state, id).await {
+        Ok(found) => found,
+        Err(response) => return response,
+    };
+
+    let filter = MessageFilter {
+        peer: query.peer,
+        before: query.before,
+    };
+
+    match repository.delete_history(&user.username, &filter).await {
+        Ok(count) => {
+            info!("API: Deleted {} messages of {}", count, user.username);
+            Json(ApiResponse::success(MessagesAffectedResponse { count })).into_response()
+        }
+        Err(e) => {
+            error!("API: Failed to delete messages: {}", e);
+            StatusCode::INTERNAL_SERVER_ERROR.into_response()
+        }
+    }
+}
+
+/// Get a user's unread message counts
+pub async fn get_unread_messages(State(state): State<AppState>, Path(id): Path<i32>) -> Response {
+    let (repository, user) = match repository_and_user(&state, id).await {
+        Ok(found) => found,
+        Err(response) => return response,
+    };
+
+    match repository.unread_counts(&user.username).await {
+        Ok(by_sender) => Json(ApiResponse::success(UnreadCountsResponse {
+            total: by_sender.values().sum(),
+            by_sender,
+        }))
+        .into_response(),
+        Err(e) => {
+            error!("API: Failed to count unread messages: {}", e);
+            StatusCode::INTERNAL_SERVER_ERROR.into_response()
+        }
+    }
+}
+
+/// Mark the messages a user received from `peer` as read
+pub async fn mark_messages_read(
+    State(state): State<AppState>,
+    Path(id): Path<i32>,
+    Query(query): Query<MarkMessagesReadQuery>,
+) -> Response {
+    let (repository, user) = match repository_and_user(&state, id).await {
+        Ok(found) => found,
+        Err(response) => return response,
+    };
+
+    match repository.mark_read(&user.username, &query.peer).await {
+        Ok(count) => Json(ApiResponse::success(MessagesAffectedResponse { count })).into_response(),
+        Err(e) => {
+            error!("API: Failed to mark messages read: {}", e);
+            StatusCode::INTERNAL_SERVER_ERROR.into_response()
+        }
+    }
+}
@@ -38,6 +38,22 @@ pub fn init_metrics() -> PrometheusHandle {
         "sip_registered_users",
         "Number of currently registered SIP users"
     );
+    describe_counter!(
+        "registration_events_total",
+        "Registrar binding events by type (added, refreshed, expired, removed, churn)"
+    );
+    describe_counter!(
+        "fraud_violations_total",
+        "Fraud rule violations on outbound calls by rule and action"
+    );
+    describe_counter!(
+        "fraud_blocked_calls_total",
+        "Outbound calls blocked by a fraud suspension"
+    );
+    describe_gauge!(
+        "fraud_suspended_users",
+        "Number of users with outbound calling suspended"
+    );
     describe_counter!(
         "sip_registrations_total",
         "Total number of SIP registrations"
@@ -54,6 +70,95 @@ pub fn init_metrics() -> PrometheusHandle {
         "sip_calls_failed",
         "Total number of SIP calls that failed"
     );
+    describe_gauge!(
+        "sip_tcp_connections_open",
+        "Number of currently open SIP TCP connections"
+    );
+    describe_counter!(
+        "sip_tcp_connection_reuse_total",
+        "Total number of SIP messages sent over an existing TCP connection"
+    );
+    describe_counter!(
+        "sip_tcp_connections_evicted_total",
+        "Total number of SIP TCP connections evicted by the connection limit"
+    );
+    describe_counter!(
+        "sip_quirks_applied_total",
+        "Interop workarounds applied to SIP messages by rule and quirk"
+    );
+    describe_gauge!(
+        "db_degraded_mode",
+        "Whether the database is unavailable and the server is in degraded mode (1) or not (0)"
+    );
+    describe_gauge!(
+        "cdr_queue_pending",
+        "Number of CDR writes queued while the database is degraded"
+    );
+    describe_counter!(
+        "cdr_queue_dropped_total",
+        "Total number of queued CDR writes dropped (queue full or permanent error)"
+    );
+    describe_counter!(
+        "sip_auth_degraded_cache_hits_total",
+        "Total number of SIP authentications served from the degraded-mode HA1 cache"
+    );
+    describe_counter!(
+        "cdr_retention_rows_purged_total",
+        "Total number of CDRs deleted or anonymized by retention, by action"
+    );
+    describe_histogram!(
+        "cdr_retention_duration_seconds",
+        "Duration of CDR retention runs"
+    );
+    describe_counter!(
+        "lnp_lookups_total",
+        "Number portability lookups by outcome (ported, not_ported, error, over_budget)"
+    );
+    describe_histogram!(
+        "lnp_lookup_duration_seconds",
+        "Duration of number portability lookups"
+    );
+    describe_counter!(
+        "lnp_cache_lookups_total",
+        "Number portability cache lookups by result (hit, miss)"
+    );
+    describe_gauge!("lnp_cache_entries", "Entries in the number portability cache");
+    describe_histogram!(
+        "call_setup_latency_seconds",
+        "Time from the INVITE to each setup milestone, by milestone, tenant and trunk"
+    );
+    describe_gauge!(
+        "channels_in_use",
+        "Trunk channels taken by calls, by trunk and direction (inbound, outbound)"
+    );
+    describe_counter!(
+        "sip_dns_queries_total",
+        "DNS queries for SIP destinations by record type (NAPTR, SRV, A)"
+    );
+    describe_counter!(
+        "sip_dns_target_failures_total",
+        "Resolved SIP targets that were unreachable or answered 503"
+    );
+    describe_counter!(
+        "media_opus_decode_packets_total",
+        "Received Opus packets by outcome (decoded, recovered by FEC, concealed)"
+    );
+    describe_counter!(
+        "media_codec_fallbacks_total",
+        "Calls moved off Opus after decode errors, by result (fell_back, failed)"
+    );
+    describe_counter!(
+        "sip_warm_transfers_total",
+        "Warm transfers settled, by outcome (completed, merged, cancelled, failed, abandoned)"
+    );
+    describe_counter!(
+        "api_idempotent_requests_total",
+        "API requests sent with an Idempotency-Key, by outcome (executed, replayed, mismatch, in_progress)"
+    );
+    describe_counter!(
+        "media_bandwidth_warnings_total",
+        "WebRTC legs whose bandwidth estimate fell below the usable bitrate"
+    );
 
     handle
 }
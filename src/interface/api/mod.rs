@@ -2,25 +2,67 @@
 
 // Temporarily disabled - under development
 // pub mod call_queue;
+pub mod agent_state_handler;
+pub mod announcement_handler;
+pub mod audio_handler;
+pub mod auto_attendant_handler;
+pub mod branding_handler;
+pub mod call_debug_handler;
+pub mod call_history_handler;
 pub mod calls_handler;
+pub mod campaign_handler;
 pub mod cdr_dto;
 pub mod cdr_handler;
+pub mod class_of_service_handler;
+pub mod config_report_handler;
 // pub mod conference;
 pub mod conference_handler;
+pub mod device_handler;
+pub mod diagnostics_handler;
+pub mod directory_handler;
+pub mod enrollment_handler;
+pub mod feature_code_handler;
+pub mod fraud_handler;
+pub mod header_rules_handler;
+pub mod idempotency;
 pub mod jsonrpc;
+pub mod lnp_handler;
+pub mod maintenance_handler;
+pub mod messages_handler;
 pub mod metrics_handler;
 pub mod monitoring;
+pub mod openapi;
+pub mod pagination;
+pub mod queue_callback_handler;
+pub mod queue_report_handler;
+pub mod readiness;
+pub mod replication_handler;
 pub mod rest;
 pub mod router;
+pub mod privacy_handler;
+pub mod routing_lint_handler;
+pub mod routing_simulation_handler;
+pub mod scheduled_call_handler;
 // pub mod sip_trunk;
+pub mod speed_dial_handler;
+pub mod storage_handler;
+pub mod survey_handler;
+pub mod survivability_handler;
+pub mod timezone;
 // pub mod tenant;
+pub mod transcription_handler;
+pub mod transfer_handler;
+pub mod trunk_handler;
 pub mod user_dto;
 pub mod user_handler;
 // pub mod user_import;
 // pub mod voicemail;
+pub mod voicemail_handler;
+pub mod voicemail_list_handler;
 // pub mod webrtc_signaling;
 pub mod websocket;
 pub mod ws_handler;
+pub mod ws_protocol;
 
 // pub use call_queue::{call_queue_router, CallQueueApiState};
 // pub use conference::{conference_router, ConferenceApiState};
@@ -11,6 +11,7 @@ use std::time::{Duration, SystemTime, UNIX_EPOCH};
 use tokio::sync::RwLock;
 use tracing::info;
 
+use super::cdr_dto::ApiResponse;
 use super::user_handler::AppState;
 
 /// System metrics
@@ -78,6 +79,9 @@ pub struct SystemHealth {
     pub registration_metrics: RegistrationMetrics,
     pub auth_metrics: AuthMetrics,
     pub media_metrics: MediaMetrics,
+    /// Branch node running without its upstream
+    #[serde(default)]
+    pub survivable: bool,
     pub warnings: Vec<String>,
     pub errors: Vec<String>,
 }
@@ -132,6 +136,7 @@ impl SystemHealth {
                 packet_loss_percent: 0.0,
                 average_jitter_ms: 0.0,
             },
+            survivable: false,
             warnings: Vec::new(),
             errors: Vec::new(),
         }
@@ -195,6 +200,10 @@ impl SystemHealth {
             self.errors.push(format!("Critical packet loss: {:.1}%", self.media_metrics.packet_loss_percent));
         }
 
+        if self.survivable {
+            self.warnings.push("Upstream unreachable: survivability mode".to_string());
+        }
+
         // Update overall status
         self.status = if !self.errors.is_empty() {
             "unhealthy".to_string()
@@ -357,6 +366,10 @@ pub async fn get_system_health(
         }
     }
 
+    if let Some(ref survivability) = state.survivability {
+        health.survivable = survivability.is_active();
+    }
+
     // Check health status
     health.check_health();
 
@@ -445,6 +458,30 @@ pub async fn get_prometheus_metrics(
     (StatusCode::OK, metrics).into_response()
 }
 
+/// Codec profile of new calls, the load it was chosen on and the thresholds
+pub async fn get_capacity(State(state): State<AppState>) -> impl IntoResponse {
+    match &state.capacity {
+        Some(monitor) => (StatusCode::OK, Json(ApiResponse::success(monitor.snapshot()))).into_response(),
+        None => (
+            StatusCode::SERVICE_UNAVAILABLE,
+            Json(ApiResponse::<()>::error("Capacity monitoring not available".to_string())),
+        )
+            .into_response(),
+    }
+}
+
+/// Call setup latency SLOs, histograms and breach history
+pub async fn get_setup_latency(State(state): State<AppState>) -> impl IntoResponse {
+    match &state.setup_latency {
+        Some(monitor) => (StatusCode::OK, Json(ApiResponse::success(monitor.status()))).into_response(),
+        None => (
+            StatusCode::SERVICE_UNAVAILABLE,
+            Json(ApiResponse::<()>::error("Setup latency monitoring not available".to_string())),
+        )
+            .into_response(),
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -0,0 +1,1057 @@
+//! OpenAPI description of the REST API
+//!
+//! `GET /api/openapi.json` serves an OpenAPI 3.0 document built from the
+//! operation table in [`operations`] and the [`ApiSchema`] implementations
+//! next to the DTOs. Every route of [`build_router`] needs an entry in the
+//! table; a test compares the two, so a route added without one fails CI.
+//!
+//! With `server.swagger_ui` enabled, `GET /api/admin/docs` serves Swagger
+//! UI for the document. Credentials are checked as for diagnostics.
+//!
+//! [`build_router`]: super::router::build_router
+
+use super::calls_handler::CallStatsResponse;
+use super::cdr_dto::{ApiResponse, CdrResponse};
+use super::conference_handler::{
+    ConferenceDetailsResponse, ConferenceResponse, CreateConferenceRequest,
+    JoinConferenceRequest, JoinConferenceResponse, MuteRequest, ParticipantResponse,
+    RaiseHandResponse,
+};
+use super::diagnostics_handler::{authorize, client_ip};
+use super::idempotency;
+use super::user_dto::{
+    ChangePasswordRequest, CreateUserRequest, DeleteResponse, UpdateUserRequest, UserResponse,
+};
+use super::user_handler::{
+    AppState, BindingInfo, OnlineCountResponse, RegistrationHistoryResponse, RegistrationInfo,
+    UserRegistrationStatus,
+};
+use crate::infrastructure::protocols::sip::{ActiveCallInfo, RegistrationEvent};
+use axum::{
+    extract::State,
+    http::{HeaderMap, StatusCode},
+    response::{Html, IntoResponse, Response},
+    Json,
+};
+use serde_json::{json, Map, Value};
+use std::sync::OnceLock;
+use tracing::error;
+
+/// Schema of a type in the OpenAPI document
+pub trait ApiSchema {
+    /// Name under `#/components/schemas`
+    const NAME: &'static str;
+
+    fn schema() -> Value;
+}
+
+pub fn string() -> Value {
+    json!({ "type": "string" })
+}
+
+pub fn integer() -> Value {
+    json!({ "type": "integer", "format": "int64" })
+}
+
+pub fn number() -> Value {
+    json!({ "type": "number", "format": "double" })
+}
+
+pub fn boolean() -> Value {
+    json!({ "type": "boolean" })
+}
+
+pub fn date_time() -> Value {
+    json!({ "type": "string", "format": "date-time" })
+}
+
+pub fn uuid() -> Value {
+    json!({ "type": "string", "format": "uuid" })
+}
+
+/// A string out of `values`
+pub fn one_of(values: &[&str]) -> Value {
+    json!({ "type": "string", "enum": values })
+}
+
+pub fn array(items: Value) -> Value {
+    json!({ "type": "array", "items": items })
+}
+
+/// Any JSON value
+pub fn any() -> Value {
+    json!({})
+}
+
+/// `schema` or null
+pub fn nullable(mut schema: Value) -> Value {
+    // Siblings of a $ref are ignored
+    if schema.get("$ref").is_some() {
+        return json!({ "allOf": [schema], "nullable": true });
+    }
+    schema["nullable"] = Value::Bool(true);
+    schema
+}
+
+/// An object of `properties`; those that are not nullable are required
+pub fn object(properties: Value) -> Value {
+    let required: Vec<String> = properties
+        .as_object()
+        .map(|properties| {
+            properties
+                .iter()
+                .filter(|(_, schema)| schema.get("nullable") != Some(&Value::Bool(true)))
+                .map(|(name, _)| name.clone())
+                .collect()
+        })
+        .unwrap_or_default();
+    let mut schema = json!({ "type": "object", "properties": properties });
+    if !required.is_empty() {
+        schema["required"] = json!(required);
+    }
+    schema
+}
+
+pub fn reference<T: ApiSchema>() -> Value {
+    json!({ "$ref": format!("#/components/schemas/{}", T::NAME) })
+}
+
+/// `data` in the [`ApiResponse`] envelope
+pub fn envelope(data: Value) -> Value {
+    json!({
+        "allOf": [
+            { "$ref": "#/components/schemas/ApiResponse" },
+            { "type": "object", "properties": { "data": data } },
+        ]
+    })
+}
+
+/// A page of `items` in the envelope
+pub fn page(items: Value) -> Value {
+    envelope(json!({
+        "allOf": [
+            { "$ref": "#/components/schemas/PageResponse" },
+            { "type": "object", "properties": { "items": array(items) } },
+        ]
+    }))
+}
+
+/// Credentials an operation takes
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Auth {
+    None,
+    /// HTTP Basic; admin routes also require `system:config`
+    Basic,
+    /// Provisioning token of a phone
+    DeviceToken,
+}
+
+/// One method of one route
+#[derive(Debug, Clone)]
+pub struct Operation {
+    pub method: &'static str,
+    /// Path in router syntax (`/users/:id`)
+    pub path: &'static str,
+    tag: &'static str,
+    summary: &'static str,
+    auth: Auth,
+    query: Vec<(&'static str, Value, &'static str)>,
+    paginated: bool,
+    request: Option<(&'static str, Value)>,
+    status: u16,
+    /// Content type and schema of the success response; none for 204
+    response: Option<(&'static str, Value)>,
+}
+
+fn operation(
+    method: &'static str,
+    path: &'static str,
+    tag: &'static str,
+    summary: &'static str,
+) -> Operation {
+    Operation {
+        method,
+        path,
+        tag,
+        summary,
+        auth: Auth::None,
+        query: Vec::new(),
+        paginated: false,
+        request: None,
+        status: 200,
+        response: Some(("application/json", envelope(any()))),
+    }
+}
+
+fn get(path: &'static str, tag: &'static str, summary: &'static str) -> Operation {
+    operation("get", path, tag, summary)
+}
+
+fn post(path: &'static str, tag: &'static str, summary: &'static str) -> Operation {
+    operation("post", path, tag, summary)
+}
+
+fn put(path: &'static str, tag: &'static str, summary: &'static str) -> Operation {
+    operation("put", path, tag, summary)
+}
+
+fn patch(path: &'static str, tag: &'static str, summary: &'static str) -> Operation {
+    operation("patch", path, tag, summary)
+}
+
+fn delete(path: &'static str, tag: &'static str, summary: &'static str) -> Operation {
+    operation("delete", path, tag, summary)
+}
+
+impl Operation {
+    fn basic(mut self) -> Self {
+        self.auth = Auth::Basic;
+        self
+    }
+
+    fn device(mut self) -> Self {
+        self.auth = Auth::DeviceToken;
+        self
+    }
+
+    fn query(mut self, name: &'static str, schema: Value, description: &'static str) -> Self {
+        self.query.push((name, schema, description));
+        self
+    }
+
+    /// Time zone of dates in the query and times in the response
+    fn tz(self) -> Self {
+        self.query("tz", string(), "IANA time zone, e.g. Europe/Berlin")
+    }
+
+    /// A list endpoint answering pages of `items`
+    fn paginated(mut self, items: Value) -> Self {
+        self.paginated = true;
+        self.response = Some(("application/json", page(items)));
+        self
+    }
+
+    fn body(mut self, schema: Value) -> Self {
+        self.request = Some(("application/json", schema));
+        self
+    }
+
+    fn multipart(mut self, schema: Value) -> Self {
+        self.request = Some(("multipart/form-data", schema));
+        self
+    }
+
+    /// `data` of the envelope
+    fn returns(mut self, data: Value) -> Self {
+        self.response = Some(("application/json", envelope(data)));
+        self
+    }
+
+    /// A JSON body outside the envelope
+    fn returns_bare(mut self, schema: Value) -> Self {
+        self.response = Some(("application/json", schema));
+        self
+    }
+
+    /// A body that is not JSON
+    fn produces(mut self, content_type: &'static str) -> Self {
+        let schema = if content_type.starts_with("text/") || content_type.contains("xml") {
+            string()
+        } else {
+            json!({ "type": "string", "format": "binary" })
+        };
+        self.response = Some((content_type, schema));
+        self
+    }
+
+    fn status(mut self, status: u16) -> Self {
+        self.status = status;
+        self
+    }
+
+    fn no_content(mut self) -> Self {
+        self.status = 204;
+        self.response = None;
+        self
+    }
+
+    /// Path in OpenAPI syntax (`/users/{id}`)
+    pub fn openapi_path(&self) -> String {
+        self.path
+            .split('/')
+            .map(|segment| match segment.strip_prefix(':') {
+                Some(name) => format!("{{{}}}", name),
+                None => segment.to_string(),
+            })
+            .collect::<Vec<_>>()
+            .join("/")
+    }
+
+    fn path_params(&self) -> impl Iterator<Item = &'static str> {
+        self.path.split('/').filter_map(|segment| segment.strip_prefix(':'))
+    }
+
+    fn to_json(&self) -> Value {
+        let mut parameters: Vec<Value> = self
+            .path_params()
+            .map(|name| json!({ "name": name, "in": "path", "required": true, "schema": string() }))
+            .collect();
+        for (name, schema, description) in &self.query {
+            parameters.push(json!({
+                "name": name,
+                "in": "query",
+                "description": description,
+                "schema": schema,
+            }));
+        }
+        if self.paginated {
+            for name in ["Limit", "Offset", "Cursor", "Count", "Sort", "Fields"] {
+                parameters.push(json!({ "$ref": format!("#/components/parameters/{}", name) }));
+            }
+        }
+        if self.method != "get" && idempotency::guarded(self.path) {
+            parameters.push(json!({ "$ref": "#/components/parameters/IdempotencyKey" }));
+        }
+
+        let mut responses = Map::new();
+        let success = match &self.response {
+            Some((content_type, schema)) => json!({
+                "description": "Success",
+                "content": { *content_type: { "schema": schema } },
+            }),
+            None => json!({ "description": "Success" }),
+        };
+        responses.insert(self.status.to_string(), success);
+        let mut errors = Vec::new();
+        if self.request.is_some() || self.paginated || !self.query.is_empty() {
+            errors.push(("400", "BadRequest"));
+        }
+        match self.auth {
+            Auth::None => {}
+            Auth::Basic => errors.extend([("401", "Unauthorized"), ("403", "Forbidden")]),
+            Auth::DeviceToken => errors.push(("401", "Unauthorized")),
+        }
+        if self.path.contains(":") {
+            errors.push(("404", "NotFound"));
+        }
+        errors.push(("503", "ServiceUnavailable"));
+        for (status, name) in errors {
+            responses.insert(
+                status.to_string(),
+                json!({ "$ref": format!("#/components/responses/{}", name) }),
+            );
+        }
+
+        let mut operation = json!({
+            "tags": [self.tag],
+            "summary": self.summary,
+            "responses": responses,
+        });
+        if !parameters.is_empty() {
+            operation["parameters"] = json!(parameters);
+        }
+        if let Some((content_type, schema)) = &self.request {
+            operation["requestBody"] = json!({
+                "required": true,
+                "content": { *content_type: { "schema": schema } },
+            });
+        }
+        match self.auth {
+            Auth::None => {}
+            Auth::Basic => operation["security"] = json!([{ "basicAuth": [] }]),
+            Auth::DeviceToken => {
+                operation["security"] = json!([{ "deviceToken": [] }, { "deviceTokenQuery": [] }])
+            }
+        }
+        operation
+    }
+}
+
+/// Every operation of the API
+pub fn operations() -> Vec<Operation> {
+    let user = || reference::<UserResponse>();
+    let cdr = || reference::<CdrResponse>();
+    let conference = || reference::<ConferenceResponse>();
+
+    vec![
+        // Health
+        get("/health", "health", "Health check").returns(string()),
+        get("/readyz", "health", "Readiness check").returns_bare(any()),
+        // Users
+        post("/users", "users", "Create a user")
+            .body(reference::<CreateUserRequest>())
+            .returns(user())
+            .status(201),
+        get("/users", "users", "List users")
+            .query("realm", string(), "Only users of this realm")
+            .paginated(user()),
+        get("/users/:id", "users", "Get a user by ID").returns(user()),
+        put("/users/:id", "users", "Update a user")
+            .body(reference::<UpdateUserRequest>())
+            .returns(user()),
+        delete("/users/:id", "users", "Delete a user").returns(reference::<DeleteResponse>()),
+        get("/users/username/:username", "users", "Get a user by username").returns(user()),
+        post("/users/:id/password", "users", "Change a user's password")
+            .body(reference::<ChangePasswordRequest>())
+            .returns(string()),
+        put("/users/:id/enabled/:enabled", "users", "Enable or disable a user").returns(user()),
+        get("/users/online", "registrations", "Registered users and their bindings")
+            .returns(array(reference::<RegistrationInfo>())),
+        get("/users/online/count", "registrations", "Number of registered users")
+            .returns(reference::<OnlineCountResponse>()),
+        get("/users/:username/status", "registrations", "Registration status of a user")
+            .returns(reference::<UserRegistrationStatus>()),
+        get("/users/:id/call-history", "users", "Call history of a user").tz(),
+        post("/users/:id/call-history/read", "users", "Mark a user's missed calls as read"),
+        get(
+            "/users/:id/registrations/history",
+            "registrations",
+            "Recent registration events of a user",
+        )
+        .returns(reference::<RegistrationHistoryResponse>()),
+        get("/users/:id/messages", "messages", "A user's messages, newest first"),
+        delete("/users/:id/messages", "messages", "Delete a user's messages"),
+        get("/users/:id/messages/unread", "messages", "A user's unread message counts"),
+        post("/users/:id/messages/read", "messages", "Mark messages from a peer as read"),
+        get("/users/:id/voicemail", "voicemail", "A user's voicemail messages").basic(),
+        patch(
+            "/users/:id/voicemail/:message_id",
+            "voicemail",
+            "Mark a message read or unread",
+        )
+        .basic(),
+        delete("/users/:id/voicemail/:message_id", "voicemail", "Delete a message").basic(),
+        get(
+            "/users/:id/voicemail/:message_id/audio",
+            "voicemail",
+            "Stream a message's recording",
+        )
+        .basic()
+        .produces("audio/wav"),
+        post(
+            "/users/:id/voicemail/:message_id/forward",
+            "voicemail",
+            "Forward a copy of a message to another mailbox",
+        )
+        .basic(),
+        get("/users/:id/fraud/status", "fraud", "Fraud counters and suspension of a user"),
+        post("/users/:id/fraud/clear", "fraud", "Re-enable outbound calling for a user"),
+        post("/users/:id/debug", "debug", "Debug a user's next calls"),
+        get("/users/:id/speed-dials", "speed-dials", "A user's speed dials"),
+        post("/users/:id/speed-dials", "speed-dials", "Create a personal speed dial").status(201),
+        put(
+            "/users/:id/speed-dials/:speed_dial_id",
+            "speed-dials",
+            "Update a personal speed dial",
+        ),
+        delete(
+            "/users/:id/speed-dials/:speed_dial_id",
+            "speed-dials",
+            "Delete a personal speed dial",
+        ),
+        // Company speed dials
+        get("/speed-dials", "speed-dials", "Company-wide short codes"),
+        post("/speed-dials", "speed-dials", "Create a company-wide short code").status(201),
+        put("/speed-dials/:id", "speed-dials", "Update a company-wide short code"),
+        delete("/speed-dials/:id", "speed-dials", "Delete a company-wide short code"),
+        // CDRs
+        get("/cdrs", "cdrs", "List CDRs")
+            .query("caller_username", string(), "Calls from this user")
+            .query("callee_username", string(), "Calls to this user")
+            .query("direction", string(), "Call direction")
+            .query("status", string(), "Call status")
+            .query("start_time_from", string(), "RFC 3339 time or date")
+            .query("start_time_to", string(), "RFC 3339 time or date (exclusive)")
+            .query("min_duration", integer(), "Shortest call duration in seconds")
+            .tz()
+            .paginated(cdr()),
+        get("/cdrs/stats", "cdrs", "CDR statistics per day")
+            .query("from", string(), "RFC 3339 time or date")
+            .query("to", string(), "RFC 3339 time or date (exclusive)")
+            .tz(),
+        get("/cdrs/suspect", "cdrs", "Answered calls that may be falsely answered")
+            .query("from", string(), "RFC 3339 time or date")
+            .query("to", string(), "RFC 3339 time or date (exclusive)")
+            .query("max_duration", integer(), "Longest reported duration in seconds")
+            .query("trunk", string(), "Only calls over this trunk")
+            .tz(),
+        get("/cdrs/:id", "cdrs", "Get a CDR").tz().returns(cdr()),
+        get("/cdrs/call-id/:call_id", "cdrs", "Get a CDR by Call-ID").tz().returns(cdr()),
+        get("/cdrs/export/csv", "cdrs", "Export CDRs as CSV").tz().produces("text/csv"),
+        get("/cdrs/export/json", "cdrs", "Export CDRs as JSON")
+            .tz()
+            .returns_bare(array(cdr())),
+        // Queue reports and callbacks
+        get("/queues/:id/reports", "queues", "Service level and wait figures of a queue"),
+        get("/agents/:id/reports", "queues", "Talk time and occupancy of an agent"),
+        get("/api/queues/:id/callbacks", "queues", "Pending callbacks of a queue"),
+        delete(
+            "/api/queues/:id/callbacks/:callback_id",
+            "queues",
+            "Cancel a pending callback",
+        ),
+        get("/api/agents/:id/state-history", "queues", "Availability changes of an agent"),
+        get(
+            "/api/queues/:id/surveys/results",
+            "queues",
+            "Post-call survey answers of a queue per question and agent",
+        ),
+        // Calls
+        get("/calls", "calls", "List active calls").paginated(reference::<ActiveCallInfo>()),
+        get("/calls/:call_id", "calls", "Get an active call")
+            .returns(reference::<ActiveCallInfo>()),
+        post("/calls/:call_id/hangup", "calls", "Hang up a call").returns(string()),
+        get("/calls/:call_id/debug", "debug", "Log lines captured for a call"),
+        post("/calls/:call_id/debug", "debug", "Capture the log events of a call"),
+        get("/calls/stats", "calls", "Call statistics of today")
+            .tz()
+            .returns(reference::<CallStatsResponse>()),
+        // Audio
+        post("/audio", "audio", "Upload an audio file")
+            .multipart(object(json!({
+                "file": { "type": "string", "format": "binary" },
+                "name": string(),
+                "category": string(),
+                "tenant_id": nullable(uuid()),
+            })))
+            .status(201),
+        get("/audio", "audio", "Audio files with their usage"),
+        delete("/audio/:id", "audio", "Delete an unreferenced audio file"),
+        get("/audio/moh-classes", "audio", "MOH classes and their playlists"),
+        put("/audio/moh-classes/:name", "audio", "Load or reload a MOH class")
+            .body(object(json!({
+                "tenant": nullable(string()),
+                "files": { "type": "array", "items": string() },
+                "shuffle": { "type": "boolean" },
+                "gain_db": { "type": "number" },
+                "resume": { "type": "string", "enum": ["restart", "continue"] },
+            }))),
+        delete("/audio/moh-classes/:name", "audio", "Remove a MOH class"),
+        // Monitoring
+        get("/monitoring/health", "monitoring", "Detailed system health"),
+        get("/monitoring/prometheus", "monitoring", "Prometheus metrics").produces("text/plain"),
+        get("/api/monitoring/capacity", "monitoring", "Codec profile and media load"),
+        get("/api/monitoring/slo", "monitoring", "Call setup latency SLOs and breaches"),
+        // Admin
+        get("/api/admin/diagnostics", "admin", "Download the diagnostic bundle")
+            .basic()
+            .query("log_lines", integer(), "Log lines to include")
+            .produces("application/gzip"),
+        post("/api/admin/maintenance/cdr-retention", "admin", "Run CDR retention now").basic(),
+        post("/api/admin/privacy/erasure", "admin", "Erase a data subject's personal data")
+            .basic()
+            .body(any()),
+        post(
+            "/api/admin/header-rules/preview",
+            "admin",
+            "Headers of a sample request after the header rules",
+        )
+        .basic()
+        .body(any()),
+        get("/api/admin/debug-targets", "admin", "Calls and users being debugged").basic(),
+        get("/api/admin/config-report", "admin", "Last preflight report").basic(),
+        get("/api/admin/storage", "admin", "Storage usage per category").basic(),
+        get("/api/admin/docs", "docs", "Swagger UI for this document")
+            .basic()
+            .produces("text/html"),
+        get("/api/openapi.json", "docs", "This document").returns_bare(any()),
+        // Replication
+        get("/api/ha/status", "replication", "Role and replication lag of this node"),
+        post("/api/ha/promote", "replication", "Promote this standby node to active"),
+        // Survivability
+        get("/api/survivability", "survivability", "Whether this branch node runs without its upstream"),
+        // Trunks
+        get("/api/trunks/registrations", "trunks", "Registration state of every trunk"),
+        get("/api/trunks/channels", "trunks", "Channels in use on every trunk"),
+        get("/api/trunks/:id/registration", "trunks", "Registration state of a trunk"),
+        post("/api/trunks/:id/register", "trunks", "Register a trunk again"),
+        // Maintenance
+        get("/api/maintenance", "maintenance", "Tenants and trunks in maintenance"),
+        get("/api/tenants/:id/maintenance", "maintenance", "Maintenance state of a tenant"),
+        put("/api/tenants/:id/maintenance", "maintenance", "Put a tenant into maintenance")
+            .body(any()),
+        get("/api/trunks/:id/maintenance", "maintenance", "Maintenance state of a trunk"),
+        put("/api/trunks/:id/maintenance", "maintenance", "Put a trunk into maintenance")
+            .body(any()),
+        // Routing
+        get("/api/routing/announcements", "routing", "List announcement routes"),
+        post("/api/routing/announcements", "routing", "Create an announcement route")
+            .body(any())
+            .status(201),
+        get("/api/routing/announcements/:id", "routing", "Get an announcement route"),
+        put("/api/routing/announcements/:id", "routing", "Replace an announcement route")
+            .body(any()),
+        delete("/api/routing/announcements/:id", "routing", "Delete an announcement route"),
+        get("/api/routing/auto-attendants", "routing", "List auto-attendants"),
+        post("/api/routing/auto-attendants", "routing", "Create an auto-attendant")
+            .body(any())
+            .status(201),
+        get("/api/routing/auto-attendants/:id", "routing", "Get an auto-attendant"),
+        put("/api/routing/auto-attendants/:id", "routing", "Replace an auto-attendant")
+            .body(any()),
+        delete("/api/routing/auto-attendants/:id", "routing", "Delete an auto-attendant"),
+        get("/api/routing/lnp/:number", "routing", "Cached portability answer of a number")
+            .basic(),
+        delete("/api/routing/lnp/:number", "routing", "Flush the cached answer of a number")
+            .basic(),
+        post("/api/routing/simulate", "routing", "Trace a simulated call")
+            .basic()
+            .body(any()),
+        post("/api/routing/lint", "routing", "Check the routing configuration").basic(),
+        // Voicemail lists
+        get("/api/voicemail/lists", "voicemail", "List distribution lists"),
+        post("/api/voicemail/lists", "voicemail", "Create a distribution list")
+            .body(any())
+            .status(201),
+        get("/api/voicemail/lists/:id", "voicemail", "Get a distribution list"),
+        put("/api/voicemail/lists/:id", "voicemail", "Replace a distribution list")
+            .body(any()),
+        delete("/api/voicemail/lists/:id", "voicemail", "Delete a distribution list"),
+        // Classes of service
+        get("/api/classes-of-service", "classes-of-service", "Classes of a tenant"),
+        get(
+            "/api/users/:id/class-of-service",
+            "classes-of-service",
+            "Class of service of a user",
+        ),
+        put(
+            "/api/users/:id/class-of-service",
+            "classes-of-service",
+            "Assign a user's class of service",
+        )
+        .body(any()),
+        put(
+            "/api/users/:id/class-of-service/override",
+            "classes-of-service",
+            "Give a user another class until a given time",
+        )
+        .body(any()),
+        delete(
+            "/api/users/:id/class-of-service/override",
+            "classes-of-service",
+            "End a user's override",
+        ),
+        // Campaigns
+        get("/api/campaigns", "campaigns", "Every campaign, oldest first"),
+        post("/api/campaigns", "campaigns", "Create a campaign")
+            .body(any())
+            .status(201),
+        get("/api/campaigns/:id", "campaigns", "Get a campaign"),
+        post("/api/campaigns/:id/pause", "campaigns", "Stop placing calls"),
+        post("/api/campaigns/:id/resume", "campaigns", "Place calls again"),
+        post("/api/campaigns/:id/cancel", "campaigns", "End a campaign"),
+        // Scheduled calls
+        get("/api/scheduled-calls", "scheduled-calls", "Every schedule, soonest first"),
+        post("/api/scheduled-calls", "scheduled-calls", "Schedule a call")
+            .body(any())
+            .status(201),
+        get("/api/scheduled-calls/:id", "scheduled-calls", "Get a scheduled call"),
+        put("/api/scheduled-calls/:id", "scheduled-calls", "Change a pending schedule")
+            .body(any()),
+        delete("/api/scheduled-calls/:id", "scheduled-calls", "Delete a scheduled call")
+            .no_content(),
+        post("/api/scheduled-calls/:id/cancel", "scheduled-calls", "Stop a pending schedule"),
+        get(
+            "/api/users/:id/scheduled-calls",
+            "scheduled-calls",
+            "Scheduled calls of a user",
+        )
+        .basic(),
+        post(
+            "/api/users/:id/scheduled-calls",
+            "scheduled-calls",
+            "Schedule a wake-up call to a user",
+        )
+        .basic()
+        .body(any())
+        .status(201),
+        delete(
+            "/api/users/:id/scheduled-calls/:call_id",
+            "scheduled-calls",
+            "Cancel a user's scheduled call",
+        )
+        .basic(),
+        // Live captions
+        get("/api/calls/:call_id/transcription", "calls", "Caption session of a call"),
+        post("/api/calls/:call_id/transcription/start", "calls", "Start captioning a call"),
+        post("/api/calls/:call_id/transcription/stop", "calls", "Stop captioning a call"),
+        // Warm transfers
+        get("/api/calls/:call_id/transfer", "calls", "Transfer in progress of a call"),
+        post(
+            "/api/calls/:call_id/transfer/consult",
+            "calls",
+            "Hold a call and call a target to consult",
+        )
+        .body(any())
+        .status(201),
+        post(
+            "/api/calls/:call_id/transfer/:action",
+            "calls",
+            "Complete, merge or cancel a transfer",
+        ),
+        // Branding
+        get("/api/tenants/:id/branding", "tenants", "Branding of a tenant"),
+        patch("/api/tenants/:id/branding", "tenants", "Change parts of a tenant's branding")
+            .body(any()),
+        // Phones
+        get("/api/directory", "devices", "Search the phone directory")
+            .device()
+            .query("q", string(), "Search text"),
+        get("/directory/yealink.xml", "devices", "Remote phonebook for Yealink phones")
+            .device()
+            .produces("application/xml"),
+        get("/directory/poly.xml", "devices", "Remote phonebook for Poly phones")
+            .device()
+            .produces("application/xml"),
+        get("/provisioning/:mac", "devices", "Configuration file of a device")
+            .device()
+            .produces("text/plain"),
+        get("/api/devices/:mac", "devices", "Resync state of a device"),
+        post("/api/devices/:mac/resync", "devices", "Tell a device to fetch its configuration"),
+        get("/api/feature-codes", "devices", "Effective feature codes per tenant"),
+        post("/api/users/:id/enrollments", "devices", "Create a softphone enrollment link")
+            .basic()
+            .body(any())
+            .status(201),
+        get("/enroll/:token", "devices", "Redeem an enrollment link for a device password"),
+        get("/api/users/:id/devices", "devices", "Enrolled devices of a user").basic(),
+        delete("/api/users/:id/devices/:device_id", "devices", "Revoke an enrolled device")
+            .basic()
+            .no_content(),
+        // Conferences
+        post("/conferences", "conferences", "Create a conference room")
+            .body(reference::<CreateConferenceRequest>())
+            .returns_bare(conference())
+            .status(201),
+        get("/conferences", "conferences", "List active conferences")
+            .returns_bare(array(conference())),
+        get("/conferences/:room_id", "conferences", "Conference details with the roster")
+            .returns_bare(reference::<ConferenceDetailsResponse>()),
+        post("/conferences/:room_id/join", "conferences", "Join a conference room")
+            .body(reference::<JoinConferenceRequest>())
+            .returns_bare(reference::<JoinConferenceResponse>()),
+        post("/conferences/:room_id/end", "conferences", "End a conference").no_content(),
+        post("/conferences/leave", "conferences", "Leave a conference room")
+            .body(reference::<MuteRequest>())
+            .no_content(),
+        post("/conferences/participants/mute", "conferences", "Mute a participant")
+            .body(reference::<MuteRequest>())
+            .no_content(),
+        post("/conferences/participants/unmute", "conferences", "Unmute a participant")
+            .body(reference::<MuteRequest>())
+            .no_content(),
+        post("/conferences/participants/hand", "conferences", "Raise or lower a hand")
+            .body(reference::<MuteRequest>())
+            .returns_bare(reference::<RaiseHandResponse>()),
+        post(
+            "/conferences/:room_id/participants/:participant_id/floor",
+            "conferences",
+            "Give a participant the floor",
+        )
+        .no_content(),
+        delete(
+            "/conferences/:room_id/participants/:participant_id/floor",
+            "conferences",
+            "Take the floor from a participant",
+        )
+        .no_content(),
+        // Outside the API state
+        get("/metrics", "monitoring", "Prometheus metrics").produces("text/plain"),
+        get("/ws", "events", "WebSocket of events and call control").status(101),
+    ]
+}
+
+fn schemas() -> Map<String, Value> {
+    fn add<T: ApiSchema>(schemas: &mut Map<String, Value>) {
+        schemas.insert(T::NAME.to_string(), T::schema());
+    }
+
+    let mut schemas = Map::new();
+    schemas.insert(
+        "ApiResponse".to_string(),
+        json!({
+            "type": "object",
+            "properties": {
+                "success": boolean(),
+                "data": any(),
+                "error": string(),
+            },
+            "required": ["success"],
+        }),
+    );
+    // Older handlers answer errors without `success`
+    schemas.insert(
+        "Error".to_string(),
+        json!({
+            "type": "object",
+            "properties": {
+                "success": { "type": "boolean", "enum": [false] },
+                "error": string(),
+            },
+            "required": ["error"],
+        }),
+    );
+    schemas.insert(
+        "PageResponse".to_string(),
+        json!({
+            "type": "object",
+            "properties": {
+                "items": array(any()),
+                "limit": integer(),
+                "offset": integer(),
+                "total": integer(),
+                "next_cursor": string(),
+            },
+            "required": ["items", "limit", "offset"],
+        }),
+    );
+    add::<UserResponse>(&mut schemas);
+    add::<CreateUserRequest>(&mut schemas);
+    add::<UpdateUserRequest>(&mut schemas);
+    add::<ChangePasswordRequest>(&mut schemas);
+    add::<DeleteResponse>(&mut schemas);
+    add::<CdrResponse>(&mut schemas);
+    add::<ActiveCallInfo>(&mut schemas);
+    add::<CallStatsResponse>(&mut schemas);
+    add::<CreateConferenceRequest>(&mut schemas);
+    add::<ConferenceResponse>(&mut schemas);
+    add::<ParticipantResponse>(&mut schemas);
+    add::<ConferenceDetailsResponse>(&mut schemas);
+    add::<JoinConferenceRequest>(&mut schemas);
+    add::<JoinConferenceResponse>(&mut schemas);
+    add::<MuteRequest>(&mut schemas);
+    add::<RaiseHandResponse>(&mut schemas);
+    add::<RegistrationInfo>(&mut schemas);
+    add::<BindingInfo>(&mut schemas);
+    add::<UserRegistrationStatus>(&mut schemas);
+    add::<RegistrationHistoryResponse>(&mut schemas);
+    add::<RegistrationEvent>(&mut schemas);
+    add::<OnlineCountResponse>(&mut schemas);
+    schemas
+}
+
+fn parameters() -> Value {
+    let query = |name: &str, schema: Value, description: &str| {
+        json!({ "name": name, "in": "query", "description": description, "schema": schema })
+    };
+    json!({
+        "Limit": query("limit", integer(), "Page size"),
+        "Offset": query("offset", integer(), "Items to skip; not with cursor"),
+        "Cursor": query("cursor", string(), "next_cursor of the previous page"),
+        "Count": query("count", boolean(), "Include total"),
+        "Sort": query("sort", string(), "Field to sort by, -field for descending"),
+        "Fields": query("fields", string(), "Comma separated fields to return per item"),
+        "IdempotencyKey": {
+            "name": "Idempotency-Key",
+            "in": "header",
+            "description": "Retries with the same key get the first response back instead of running again",
+            "schema": string(),
+        },
+    })
+}
+
+fn error_responses() -> Value {
+    let error = |description: &str| {
+        json!({
+            "description": description,
+            "content": {
+                "application/json": { "schema": { "$ref": "#/components/schemas/Error" } }
+            },
+        })
+    };
+    json!({
+        "BadRequest": error("Invalid request"),
+        "Unauthorized": error("Missing or wrong credentials"),
+        "Forbidden": error("Permission system:config required"),
+        "NotFound": error("No such resource"),
+        "ServiceUnavailable": error("Service not configured, or the database is down"),
+    })
+}
+
+/// The OpenAPI document
+pub fn document() -> Value {
+    let mut paths = Map::new();
+    for operation in operations() {
+        let item = paths
+            .entry(operation.openapi_path())
+            .or_insert_with(|| json!({}));
+        item[operation.method] = operation.to_json();
+    }
+
+    json!({
+        "openapi": "3.0.3",
+        "info": {
+            "title": "YakYak API",
+            "version": env!("CARGO_PKG_VERSION"),
+        },
+        "paths": paths,
+        "components": {
+            "schemas": schemas(),
+            "parameters": parameters(),
+            "responses": error_responses(),
+            "securitySchemes": {
+                "basicAuth": {
+                    "type": "http",
+                    "scheme": "basic",
+                    "description": "User credentials; admin routes require system:config",
+                },
+                "deviceToken": {
+                    "type": "http",
+                    "scheme": "bearer",
+                    "description": "Provisioning token of a phone",
+                },
+                "deviceTokenQuery": {
+                    "type": "apiKey",
+                    "in": "query",
+                    "name": "token",
+                },
+            },
+        },
+    })
+}
+
+/// The OpenAPI document of the API
+pub async fn get_openapi() -> Json<Value> {
+    static DOCUMENT: OnceLock<Value> = OnceLock::new();
+    Json(DOCUMENT.get_or_init(document).clone())
+}
+
+const SWAGGER_UI: &str = r##"<!DOCTYPE html>
+<html>
+<head>
+  <meta charset="utf-8">
+  <title>YakYak API</title>
+  <link rel="stylesheet" href="https://unpkg.com/swagger-ui-dist@5/swagger-ui.css">
+</head>
+<body>
+  <div id="swagger-ui"></div>
+  <script src="https://unpkg.com/swagger-ui-dist@5/swagger-ui-bundle.js"></script>
+  <script>
+    window.ui = SwaggerUIBundle({ url: "/api/openapi.json", dom_id: "#swagger-ui" });
+  </script>
+</body>
+</html>
+"##;
+
+/// Swagger UI for the document (requires `server.swagger_ui` and
+/// `system:config`)
+pub async fn swagger_ui(State(state): State<AppState>, headers: HeaderMap) -> Response {
+    if !state.swagger_ui {
+        return StatusCode::NOT_FOUND.into_response();
+    }
+    let Some(diagnostics) = state.diagnostics.clone() else {
+        error!("Admin authentication not available");
+        return (
+            StatusCode::SERVICE_UNAVAILABLE,
+            Json(ApiResponse::<()>::error(
+                "Admin authentication not available".to_string(),
+            )),
+        )
+            .into_response();
+    };
+
+    let ip = client_ip(&headers);
+    if let Err(response) = authorize(&state, &diagnostics, &headers, &ip, "api_docs", "view").await {
+        return response;
+    }
+    Html(SWAGGER_UI).into_response()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use regex::Regex;
+    use std::collections::BTreeSet;
+
+    /// (method, path) of every route in the router source
+    fn router_routes() -> BTreeSet<(String, String)> {
+        let source = include_str!("router.rs");
+        let source = source.split("#[cfg(test)]").next().unwrap();
+        let path = Regex::new(r#"^\s*"([^"]+)""#).unwrap();
+        let method = Regex::new(r"\b(get|post|put|patch|delete)\(").unwrap();
+
+        let mut routes = BTreeSet::new();
+        for (start, _) in source.match_indices(".route(") {
+            let args = &source[start + ".route(".len()..];
+            let mut depth = 1;
+            let end = args
+                .char_indices()
+                .find(|(_, c)| {
+                    match c {
+                        '(' => depth += 1,
+                        ')' => depth -= 1,
+                        _ => {}
+                    }
+                    depth == 0
+                })
+                .map(|(i, _)| i)
+                .unwrap();
+            let args = &args[..end];
+            let path = &path.captures(args).expect("route without a literal path")[1];
+            for captures in method.captures_iter(args) {
+                routes.insert((captures[1].to_string(), path.to_string()));
+            }
+        }
+        routes
+    }
+
+    #[test]
+    fn test_every_route_documented() {
+        let routes = router_routes();
+        let documented: BTreeSet<_> = operations()
+            .iter()
+            .map(|op| (op.method.to_string(), op.path.to_string()))
+            .collect();
+        assert!(routes.len() > 100);
+        let missing: Vec<_> = routes.difference(&documented).collect();
+        assert!(missing.is_empty(), "routes without an operation: {:?}", missing);
+        let stale: Vec<_> = documented.difference(&routes).collect();
+        assert!(stale.is_empty(), "operations without a route: {:?}", stale);
+        assert_eq!(documented.len(), operations().len(), "duplicate operations");
+    }
+
+    #[test]
+    fn test_document() {
+        let text = serde_json::to_string(&document()).unwrap();
+        let document: Value = serde_json::from_str(&text).unwrap();
+        assert_eq!(document["openapi"], "3.0.3");
+
+        let paths = document["paths"].as_object().unwrap();
+        for (path, method) in [
+            ("/users", "get"),
+            ("/users/{id}", "put"),
+            ("/cdrs", "get"),
+            ("/calls/{call_id}/hangup", "post"),
+            ("/api/calls/{call_id}/transcription/start", "post"),
+            ("/conferences/{room_id}/join", "post"),
+        ] {
+            assert!(paths[path].get(method).is_some(), "{} {} missing", method, path);
+        }
+
+        let list_users = &paths["/users"]["get"];
+        let parameters = list_users["parameters"].to_string();
+        assert!(parameters.contains("#/components/parameters/Cursor"));
+        assert!(list_users["responses"]["200"].to_string().contains("PageResponse"));
+        assert_eq!(paths["/users/{id}"]["get"]["parameters"][0]["name"], "id");
+        assert_eq!(
+            paths["/api/admin/storage"]["get"]["security"][0],
+            json!({ "basicAuth": [] })
+        );
+
+        // Every reference resolves
+        let reference = Regex::new(r##""\$ref":"#/components/(\w+)/(\w+)""##).unwrap();
+        for captures in reference.captures_iter(&text) {
+            assert!(
+                document["components"][&captures[1]].get(&captures[2]).is_some(),
+                "dangling reference {}/{}",
+                &captures[1],
+                &captures[2]
+            );
+        }
+
+        let user = &document["components"]["schemas"]["User"];
+        let required = user["required"].to_string();
+        assert!(required.contains("username"));
+        assert!(!required.contains("email"));
+    }
+}
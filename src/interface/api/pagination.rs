@@ -0,0 +1,481 @@
+//! Pagination, sorting and field selection for list endpoints
+//!
+//! Every list endpoint takes the same query parameters and answers with a
+//! [`PageResponse`]; new list endpoints must extract [`Pagination`] and
+//! answer through [`Pagination::respond`] instead of inventing their own.
+//!
+//! - `limit`: page size, 1 to `server.pagination.max_limit` (default
+//!   `server.pagination.default_limit`)
+//! - `offset`: items to skip, or `cursor`: the opaque `next_cursor` of the
+//!   previous page (not both)
+//! - `count=true`: include `total`; off by default because counting is
+//!   expensive on large tables
+//! - `sort`: a field the endpoint allows, `-field` for descending
+//! - `fields`: comma separated fields to return per item
+//!
+//! Responses carry `Link` headers (`rel="next"`, `rel="prev"`) to the
+//! neighbouring pages.
+
+use super::cdr_dto::ApiResponse;
+use super::user_handler::AppState;
+use crate::config::PaginationConfig;
+use crate::domain::shared::SortOrder;
+use axum::{
+    async_trait,
+    extract::{FromRef, FromRequestParts, OriginalUri, Query},
+    http::{header, request::Parts, StatusCode, Uri},
+    response::{IntoResponse, Response},
+    Json,
+};
+use base64::Engine;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::HashMap;
+
+const CURSOR_ENGINE: base64::engine::GeneralPurpose =
+    base64::engine::general_purpose::URL_SAFE_NO_PAD;
+
+/// One page of a list
+#[derive(Debug, Serialize, Deserialize)]
+pub struct PageResponse<T> {
+    pub items: Vec<T>,
+    pub limit: i64,
+    pub offset: i64,
+    /// Number of items across all pages, when asked for with `count=true`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub total: Option<i64>,
+    /// Cursor of the next page; absent on the last page
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub next_cursor: Option<String>,
+}
+
+/// Invalid pagination parameters, answered with 400
+#[derive(Debug, Clone, PartialEq)]
+pub struct PaginationError(pub String);
+
+impl IntoResponse for PaginationError {
+    fn into_response(self) -> Response {
+        (
+            StatusCode::BAD_REQUEST,
+            Json(ApiResponse::<()>::error(self.0)),
+        )
+            .into_response()
+    }
+}
+
+impl FromRef<AppState> for PaginationConfig {
+    fn from_ref(state: &AppState) -> Self {
+        state.pagination.clone()
+    }
+}
+
+/// Pagination parameters of a list request
+#[derive(Debug, Clone)]
+pub struct Pagination {
+    pub limit: i64,
+    pub offset: i64,
+    /// Whether the total count was asked for
+    pub count: bool,
+    sort: Option<String>,
+    fields: Option<Vec<String>>,
+    /// Request URI, for the Link headers
+    uri: Uri,
+}
+
+fn parse_number(
+    params: &HashMap<String, String>,
+    name: &str,
+) -> Result<Option<i64>, PaginationError> {
+    params
+        .get(name)
+        .map(|value| {
+            value
+                .trim()
+                .parse()
+                .map_err(|_| PaginationError(format!("{} must be a number, got '{}'", name, value)))
+        })
+        .transpose()
+}
+
+impl Pagination {
+    /// Parse and validate the parameters of `uri`
+    pub fn from_uri(uri: Uri, config: &PaginationConfig) -> Result<Self, PaginationError> {
+        let Query(params) = Query::<HashMap<String, String>>::try_from_uri(&uri)
+            .map_err(|e| PaginationError(format!("Invalid query string: {}", e)))?;
+
+        let limit = parse_number(&params, "limit")?.unwrap_or(config.default_limit);
+        if limit < 1 || limit > config.max_limit {
+            return Err(PaginationError(format!(
+                "limit must be between 1 and {}, got {}",
+                config.max_limit, limit
+            )));
+        }
+
+        let offset = match (parse_number(&params, "offset")?, params.get("cursor")) {
+            (Some(_), Some(_)) => {
+                return Err(PaginationError(
+                    "Use either offset or cursor, not both".to_string(),
+                ))
+            }
+            (Some(offset), None) if offset < 0 => {
+                return Err(PaginationError(format!(
+                    "offset must not be negative, got {}",
+                    offset
+                )))
+            }
+            (Some(offset), None) => offset,
+            (None, Some(cursor)) => decode_cursor(cursor)
+                .ok_or_else(|| PaginationError(format!("Invalid cursor '{}'", cursor)))?,
+            (None, None) => 0,
+        };
+
+        let count = match params.get("count").map(|c| c.to_lowercase()) {
+            None => false,
+            Some(c) if c == "true" || c == "1" => true,
+            Some(c) if c == "false" || c == "0" => false,
+            Some(c) => {
+                return Err(PaginationError(format!(
+                    "count must be true or false, got '{}'",
+                    c
+                )))
+            }
+        };
+
+        let fields = params
+            .get("fields")
+            .map(|fields| {
+                fields
+                    .split(',')
+                    .map(|f| f.trim().to_string())
+                    .filter(|f| !f.is_empty())
+                    .collect::<Vec<_>>()
+            })
+            .filter(|fields| !fields.is_empty());
+
+        Ok(Self {
+            limit,
+            offset,
+            count,
+            sort: params
+                .get("sort")
+                .map(|s| s.trim().to_string())
+                .filter(|s| !s.is_empty()),
+            fields,
+            uri,
+        })
+    }
+
+    /// Items to fetch: one more than the page holds, to tell whether a
+    /// next page exists without counting
+    pub fn fetch_limit(&self) -> i64 {
+        self.limit + 1
+    }
+
+    /// Requested sort order, checked against the fields the endpoint can
+    /// sort by; `default` when none was requested
+    pub fn sort(&self, allowed: &[&str], default: SortOrder) -> Result<SortOrder, PaginationError> {
+        let Some(sort) = &self.sort else {
+            return Ok(default);
+        };
+        let order = match sort.strip_prefix('-') {
+            Some(field) => SortOrder::descending(field),
+            None => SortOrder::ascending(sort.strip_prefix('+').unwrap_or(sort)),
+        };
+        if !allowed.contains(&order.field.as_str()) {
+            return Err(PaginationError(format!(
+                "Cannot sort by '{}' (allowed: {})",
+                order.field,
+                allowed.join(", ")
+            )));
+        }
+        Ok(order)
+    }
+
+    /// Answer with a page of `items`, fetched with [`Self::fetch_limit`]
+    ///
+    /// Items are reduced to the requested `fields`; an unknown field is
+    /// answered with 400.
+    pub fn respond<T: Serialize>(&self, mut items: Vec<T>, total: Option<i64>) -> Response {
+        let has_more = items.len() as i64 > self.limit;
+        items.truncate(self.limit as usize);
+        let next_offset = has_more.then(|| self.offset + self.limit);
+
+        let body = match &self.fields {
+            None => self.page(items, total, next_offset).into_response(),
+            Some(fields) => match items
+                .iter()
+                .map(|item| project(item, fields))
+                .collect::<Result<Vec<_>, _>>()
+            {
+                Ok(items) => self.page(items, total, next_offset).into_response(),
+                Err(e) => return e.into_response(),
+            },
+        };
+
+        let links = self.links(next_offset);
+        if links.is_empty() {
+            return body;
+        }
+        ([(header::LINK, links.join(", "))], body).into_response()
+    }
+
+    fn page<T: Serialize>(
+        &self,
+        items: Vec<T>,
+        total: Option<i64>,
+        next_offset: Option<i64>,
+    ) -> Json<ApiResponse<PageResponse<T>>> {
+        Json(ApiResponse::success(PageResponse {
+            items,
+            limit: self.limit,
+            offset: self.offset,
+            total,
+            next_cursor: next_offset.map(encode_cursor),
+        }))
+    }
+
+    /// Link header values of the next and previous pages
+    fn links(&self, next_offset: Option<i64>) -> Vec<String> {
+        let mut links = Vec::new();
+        if let Some(next) = next_offset {
+            links.push(format!("<{}>; rel=\"next\"", self.page_uri(next)));
+        }
+        if self.offset > 0 {
+            let prev = (self.offset - self.limit).max(0);
+            links.push(format!("<{}>; rel=\"prev\"", self.page_uri(prev)));
+        }
+        links
+    }
+
+    /// The request URI pointing at the page starting at `offset`
+    fn page_uri(&self, offset: i64) -> String {
+        let mut query: Vec<String> = self
+            .uri
+            .query()
+            .unwrap_or_default()
+            .split('&')
+            .filter(|pair| {
+                let name = pair.split('=').next().unwrap_or_default();
+                !pair.is_empty() && !matches!(name, "limit" | "offset" | "cursor")
+            })
+            .map(str::to_string)
+            .collect();
+        query.push(format!("limit={}", self.limit));
+        query.push(format!("cursor={}", encode_cursor(offset)));
+        format!("{}?{}", self.uri.path(), query.join("&"))
+    }
+}
+
+#[async_trait]
+impl<S> FromRequestParts<S> for Pagination
+where
+    PaginationConfig: FromRef<S>,
+    S: Send + Sync,
+{
+    type Rejection = PaginationError;
+
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        // Nested routers see a stripped URI; links must use the original
+        let uri = parts
+            .extensions
+            .get::<OriginalUri>()
+            .map(|original| original.0.clone())
+            .unwrap_or_else(|| parts.uri.clone());
+        Self::from_uri(uri, &PaginationConfig::from_ref(state))
+    }
+}
+
+fn encode_cursor(offset: i64) -> String {
+    CURSOR_ENGINE.encode(format!("o:{}", offset))
+}
+
+fn decode_cursor(cursor: &str) -> Option<i64> {
+    let decoded = CURSOR_ENGINE.decode(cursor.trim()).ok()?;
+    String::from_utf8(decoded)
+        .ok()?
+        .strip_prefix("o:")?
+        .parse()
+        .ok()
+        .filter(|offset| *offset >= 0)
+}
+
+/// `item` reduced to `fields`
+fn project<T: Serialize>(item: &T, fields: &[String]) -> Result<Value, PaginationError> {
+    let Value::Object(mut object) = serde_json::to_value(item)
+        .map_err(|e| PaginationError(format!("Failed to serialize item: {}", e)))?
+    else {
+        return Err(PaginationError(
+            "fields is not supported by this endpoint".to_string(),
+        ));
+    };
+    let mut projected = serde_json::Map::new();
+    for field in fields {
+        match object.remove(field) {
+            Some(value) => {
+                projected.insert(field.clone(), value);
+            }
+            None if projected.contains_key(field) => {}
+            None => return Err(PaginationError(format!("Unknown field '{}'", field))),
+        }
+    }
+    Ok(Value::Object(projected))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::{body::Body, http::Request, routing::get, Router};
+    use tower::ServiceExt;
+
+    fn parse(query: &str) -> Result<Pagination, PaginationError> {
+        let config = PaginationConfig {
+            default_limit: 20,
+            max_limit: 100,
+        };
+        Pagination::from_uri(format!("/items?{}", query).parse().unwrap(), &config)
+    }
+
+    #[derive(Serialize)]
+    struct Item {
+        id: i64,
+        name: String,
+    }
+
+    fn items(ids: std::ops::Range<i64>) -> Vec<Item> {
+        ids.map(|id| Item {
+            id,
+            name: format!("item-{}", id),
+        })
+        .collect()
+    }
+
+    async fn body(response: Response) -> Value {
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        serde_json::from_slice(&body).unwrap()
+    }
+
+    #[test]
+    fn test_validation() {
+        let page = parse("").unwrap();
+        assert_eq!((page.limit, page.offset, page.count), (20, 0, false));
+
+        let page = parse("limit=100&offset=40&count=true").unwrap();
+        assert_eq!((page.limit, page.offset, page.count), (100, 40, true));
+
+        assert_eq!(
+            parse("limit=101").unwrap_err().0,
+            "limit must be between 1 and 100, got 101"
+        );
+        assert!(parse("limit=0").is_err());
+        assert!(parse("limit=ten")
+            .unwrap_err()
+            .0
+            .contains("limit must be a number"));
+        assert!(parse("offset=-1")
+            .unwrap_err()
+            .0
+            .contains("must not be negative"));
+        assert!(parse("count=maybe").is_err());
+
+        let cursor = encode_cursor(60);
+        assert_eq!(parse(&format!("cursor={}", cursor)).unwrap().offset, 60);
+        assert!(parse(&format!("cursor={}&offset=0", cursor)).is_err());
+        assert!(parse("cursor=garbage")
+            .unwrap_err()
+            .0
+            .contains("Invalid cursor"));
+    }
+
+    #[test]
+    fn test_sort_whitelist() {
+        let allowed = ["id", "name"];
+        let default = SortOrder::ascending("id");
+
+        assert_eq!(
+            parse("").unwrap().sort(&allowed, default.clone()).unwrap(),
+            default
+        );
+        assert_eq!(
+            parse("sort=-name")
+                .unwrap()
+                .sort(&allowed, default.clone())
+                .unwrap(),
+            SortOrder::descending("name")
+        );
+        assert_eq!(
+            parse("sort=password_hash")
+                .unwrap()
+                .sort(&allowed, default)
+                .unwrap_err()
+                .0,
+            "Cannot sort by 'password_hash' (allowed: id, name)"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_limit_over_max_is_bad_request() {
+        let app = Router::new()
+            .route("/items", get(|_page: Pagination| async { "listed" }))
+            .with_state(PaginationConfig::default());
+
+        let response = app
+            .oneshot(
+                Request::builder()
+                    .uri("/items?limit=501")
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        let json = body(response).await;
+        assert_eq!(json["success"], false);
+        assert_eq!(json["error"], "limit must be between 1 and 500, got 501");
+    }
+
+    #[tokio::test]
+    async fn test_respond_pages_links_and_fields() {
+        // A full page plus one: there is a next page
+        let page = parse("limit=2&offset=2&realm=a&count=true").unwrap();
+        let response = page.respond(items(2..5), Some(9));
+        let links = response.headers()[header::LINK]
+            .to_str()
+            .unwrap()
+            .to_string();
+        assert_eq!(
+            links,
+            format!(
+                "</items?realm=a&count=true&limit=2&cursor={}>; rel=\"next\", \
+                 </items?realm=a&count=true&limit=2&cursor={}>; rel=\"prev\"",
+                encode_cursor(4),
+                encode_cursor(0)
+            )
+        );
+        let json = body(response).await;
+        assert_eq!(json["data"]["items"].as_array().unwrap().len(), 2);
+        assert_eq!(json["data"]["total"], 9);
+        assert_eq!(json["data"]["next_cursor"], encode_cursor(4));
+
+        // Last page: no next link, no cursor, no total unless asked for
+        let response = parse("limit=2").unwrap().respond(items(0..1), None);
+        assert!(response.headers().get(header::LINK).is_none());
+        let json = body(response).await;
+        assert!(json["data"].get("next_cursor").is_none());
+        assert!(json["data"].get("total").is_none());
+
+        let response = parse("fields=name").unwrap().respond(items(0..2), None);
+        let json = body(response).await;
+        assert_eq!(
+            json["data"]["items"][1],
+            serde_json::json!({"name": "item-1"})
+        );
+
+        let response = parse("fields=name,secret")
+            .unwrap()
+            .respond(items(0..2), None);
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    }
+}
@@ -0,0 +1,128 @@
+//! Privacy API handlers
+//!
+//! `POST /api/admin/privacy/erasure` erases a data subject, named by
+//! number, SIP URI or user ID, from CDRs, messages, voicemail, recordings
+//! and the audit log, and returns the signed erasure report. Credentials
+//! are checked as for diagnostics, with the `privacy:erasure` permission.
+
+use super::cdr_dto::ApiResponse;
+use super::diagnostics_handler::{authorize_with, client_ip};
+use super::user_handler::AppState;
+use crate::application::privacy::{ErasureError, ErasureRequest};
+use crate::domain::security::{SecurityEvent, SecuritySeverity};
+use crate::domain::user::Permission;
+use axum::{
+    extract::State,
+    http::{HeaderMap, StatusCode},
+    response::{IntoResponse, Response},
+    Json,
+};
+use serde::Deserialize;
+use tracing::{error, info};
+
+/// Body of an erasure request
+#[derive(Debug, Deserialize)]
+pub struct ErasureBody {
+    /// Number, SIP/tel URI or username
+    #[serde(default)]
+    pub subject: Option<String>,
+    #[serde(default)]
+    pub user_id: Option<i32>,
+    /// Report what would be erased without changing anything
+    #[serde(default)]
+    pub dry_run: bool,
+    /// Delete voicemail and recordings too; needs `confirm_media_deletion`
+    #[serde(default)]
+    pub delete_media: bool,
+    #[serde(default)]
+    pub confirm_media_deletion: bool,
+}
+
+fn error_response(status: StatusCode, message: String) -> Response {
+    (status, Json(ApiResponse::<()>::error(message))).into_response()
+}
+
+/// Erase a data subject (requires `privacy:erasure`)
+pub async fn erase_subject(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Json(body): Json<ErasureBody>,
+) -> Response {
+    let Some(diagnostics) = state.diagnostics.clone() else {
+        return error_response(
+            StatusCode::SERVICE_UNAVAILABLE,
+            "Admin authentication not available".to_string(),
+        );
+    };
+    let Some(privacy) = state.privacy.clone() else {
+        return error_response(
+            StatusCode::SERVICE_UNAVAILABLE,
+            "Privacy erasure not available".to_string(),
+        );
+    };
+
+    let ip = client_ip(&headers);
+    let action = if body.dry_run { "dry_run" } else { "erase" };
+    let username = match authorize_with(
+        &state,
+        &diagnostics,
+        &headers,
+        &ip,
+        Permission::PrivacyErasure,
+        "privacy_erasure",
+        action,
+    )
+    .await
+    {
+        Ok(username) => username,
+        Err(response) => return response,
+    };
+
+    if body.subject.is_none() && body.user_id.is_none() {
+        return error_response(
+            StatusCode::BAD_REQUEST,
+            "subject or user_id required".to_string(),
+        );
+    }
+    if body.delete_media && !body.confirm_media_deletion {
+        return error_response(
+            StatusCode::BAD_REQUEST,
+            "Deleting media cannot be undone; set confirm_media_deletion".to_string(),
+        );
+    }
+
+    let request = ErasureRequest {
+        subject: body.subject,
+        user_id: body.user_id,
+        requested_by: username.clone(),
+        dry_run: body.dry_run,
+        delete_media: body.delete_media,
+    };
+    match privacy.erase(&request).await {
+        Ok(report) => {
+            info!("API: Erasure {} {} by {}", report.id, action, username);
+            if !report.dry_run {
+                diagnostics.audit(
+                    SecurityEvent::AdminAction {
+                        admin_username: username,
+                        ip,
+                        action: action.to_string(),
+                        target: format!("privacy_erasure:{}", report.subject),
+                    },
+                    SecuritySeverity::High,
+                );
+            }
+            Json(ApiResponse::success(report)).into_response()
+        }
+        Err(e) => {
+            let status = match e {
+                ErasureError::InvalidSubject(_) => StatusCode::BAD_REQUEST,
+                ErasureError::UserNotFound(_) => StatusCode::NOT_FOUND,
+                ErasureError::NotConfigured => StatusCode::SERVICE_UNAVAILABLE,
+                ErasureError::Store { .. } => StatusCode::INTERNAL_SERVER_ERROR,
+            };
+            error!("API: Erasure failed: {}", e);
+            error_response(status, e.to_string())
+        }
+    }
+}
@@ -0,0 +1,71 @@
+//! Queue callback API handlers
+
+use super::cdr_dto::ApiResponse;
+use super::user_handler::AppState;
+use crate::domain::call_queue_engine::{CallQueueEngine, QueueEngineError};
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    Json,
+};
+use std::sync::Arc;
+use tracing::{error, info};
+use uuid::Uuid;
+
+#[allow(clippy::result_large_err)]
+pub(super) fn queue_engine(state: &AppState) -> Result<&Arc<CallQueueEngine>, Response> {
+    state.queue_engine.as_ref().ok_or_else(|| {
+        error!("Call queue engine not available");
+        (
+            StatusCode::SERVICE_UNAVAILABLE,
+            Json(ApiResponse::<()>::error(
+                "Call queues not enabled".to_string(),
+            )),
+        )
+            .into_response()
+    })
+}
+
+fn engine_error(error: QueueEngineError) -> Response {
+    let status = match error {
+        QueueEngineError::QueueNotFound | QueueEngineError::CallbackNotFound => {
+            StatusCode::NOT_FOUND
+        }
+        QueueEngineError::InvalidOperation(_) => StatusCode::CONFLICT,
+        _ => StatusCode::BAD_REQUEST,
+    };
+    (status, Json(ApiResponse::<()>::error(error.to_string()))).into_response()
+}
+
+/// Pending callbacks of a queue, in line order
+pub async fn list_queue_callbacks(State(state): State<AppState>, Path(id): Path<Uuid>) -> Response {
+    let engine = match queue_engine(&state) {
+        Ok(engine) => engine,
+        Err(response) => return response,
+    };
+
+    match engine.list_callbacks(id) {
+        Ok(callbacks) => Json(ApiResponse::success(callbacks)).into_response(),
+        Err(e) => engine_error(e),
+    }
+}
+
+/// Cancel a callback that is not being dialed
+pub async fn cancel_queue_callback(
+    State(state): State<AppState>,
+    Path((id, callback_id)): Path<(Uuid, Uuid)>,
+) -> Response {
+    let engine = match queue_engine(&state) {
+        Ok(engine) => engine,
+        Err(response) => return response,
+    };
+
+    match engine.cancel_callback(id, callback_id) {
+        Ok(callback) => {
+            info!("API: Cancelled callback {} in queue {}", callback_id, id);
+            Json(ApiResponse::success(callback)).into_response()
+        }
+        Err(e) => engine_error(e),
+    }
+}
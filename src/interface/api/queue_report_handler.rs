@@ -0,0 +1,182 @@
+//! Queue and agent reporting API handlers
+
+use super::cdr_dto::ApiResponse;
+use super::user_handler::AppState;
+use crate::domain::queue_reporting::{
+    build_agent_report, build_queue_report, parse_interval, QueueEventRepository,
+    MAX_REPORT_INTERVALS, REPORT_LOOKAHEAD,
+};
+use axum::{
+    extract::{Path, Query, State},
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    Json,
+};
+use chrono::{DateTime, Duration, Utc};
+use serde::Deserialize;
+use std::sync::Arc;
+use tracing::error;
+use uuid::Uuid;
+
+/// Query parameters for queue and agent reports
+#[derive(Debug, Deserialize)]
+pub struct ReportQuery {
+    /// Start of the report window (default: 24 hours before `to`)
+    pub from: Option<DateTime<Utc>>,
+    /// End of the report window (default: now)
+    pub to: Option<DateTime<Utc>>,
+    /// Interval length: `15m`, `1h`, `1d` or seconds (default: `1h`)
+    pub interval: Option<String>,
+}
+
+/// Validated report window
+struct ReportWindow {
+    from: DateTime<Utc>,
+    to: DateTime<Utc>,
+    interval: Duration,
+}
+
+fn bad_request(message: String) -> Response {
+    (StatusCode::BAD_REQUEST, Json(ApiResponse::<()>::error(message))).into_response()
+}
+
+#[allow(clippy::result_large_err)]
+fn report_window(query: &ReportQuery) -> Result<ReportWindow, Response> {
+    let to = query.to.unwrap_or_else(Utc::now);
+    let from = query.from.unwrap_or(to - Duration::hours(24));
+    if from >= to {
+        return Err(bad_request("'from' must be before 'to'".to_string()));
+    }
+
+    let interval = match query.interval.as_deref() {
+        Some(value) => parse_interval(value)
+            .ok_or_else(|| bad_request(format!("Invalid interval '{}'", value)))?,
+        None => Duration::hours(1),
+    };
+    let intervals = (to - from).num_seconds() / interval.num_seconds();
+    if intervals > MAX_REPORT_INTERVALS {
+        return Err(bad_request(format!(
+            "Report would have {} intervals (max {})",
+            intervals, MAX_REPORT_INTERVALS
+        )));
+    }
+
+    Ok(ReportWindow { from, to, interval })
+}
+
+#[allow(clippy::result_large_err)]
+fn events_repository(state: &AppState) -> Result<&Arc<dyn QueueEventRepository>, Response> {
+    state.queue_event_repository.as_ref().ok_or_else(|| {
+        error!("Queue event repository not available");
+        (
+            StatusCode::SERVICE_UNAVAILABLE,
+            Json(ApiResponse::<()>::error("Queue reporting not enabled".to_string())),
+        )
+            .into_response()
+    })
+}
+
+/// Service level, abandonment and wait figures of a queue per interval
+pub async fn get_queue_report(
+    State(state): State<AppState>,
+    Path(id): Path<Uuid>,
+    Query(query): Query<ReportQuery>,
+) -> Response {
+    let window = match report_window(&query) {
+        Ok(window) => window,
+        Err(response) => return response,
+    };
+    let events_repo = match events_repository(&state) {
+        Ok(repo) => repo,
+        Err(response) => return response,
+    };
+    let queue_repo = match &state.call_queue_repository {
+        Some(repo) => repo,
+        None => {
+            error!("Call queue repository not available");
+            return StatusCode::SERVICE_UNAVAILABLE.into_response();
+        }
+    };
+
+    let queue = match queue_repo.get_queue(id).await {
+        Ok(Some(queue)) => queue,
+        Ok(None) => {
+            return (
+                StatusCode::NOT_FOUND,
+                Json(ApiResponse::<()>::error(format!("Queue {} not found", id))),
+            )
+                .into_response()
+        }
+        Err(e) => {
+            error!("API: Failed to get queue: {}", e);
+            return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+        }
+    };
+
+    let events = match events_repo
+        .list_for_queue(id, window.from, window.to + REPORT_LOOKAHEAD)
+        .await
+    {
+        Ok(events) => events,
+        Err(e) => {
+            error!("API: Failed to list queue events: {}", e);
+            return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+        }
+    };
+
+    let report = build_queue_report(
+        queue.id,
+        queue.service_level_threshold,
+        &events,
+        window.from,
+        window.to,
+        window.interval,
+    );
+    Json(ApiResponse::success(report)).into_response()
+}
+
+/// Calls handled, talk/wrap-up time and occupancy of an agent per interval
+pub async fn get_agent_report(
+    State(state): State<AppState>,
+    Path(id): Path<i32>,
+    Query(query): Query<ReportQuery>,
+) -> Response {
+    let window = match report_window(&query) {
+        Ok(window) => window,
+        Err(response) => return response,
+    };
+    let events_repo = match events_repository(&state) {
+        Ok(repo) => repo,
+        Err(response) => return response,
+    };
+
+    match state.user_repository.find_by_id(id).await {
+        Ok(Some(_)) => {}
+        Ok(None) => {
+            return (
+                StatusCode::NOT_FOUND,
+                Json(ApiResponse::<()>::error(format!("Agent {} not found", id))),
+            )
+                .into_response()
+        }
+        Err(e) => {
+            error!("API: Failed to get user: {}", e);
+            return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+        }
+    }
+
+    // Talk that started before the window can still overlap it
+    let events = match events_repo
+        .list_for_agent(id, window.from - REPORT_LOOKAHEAD, window.to + REPORT_LOOKAHEAD)
+        .await
+    {
+        Ok(events) => events,
+        Err(e) => {
+            error!("API: Failed to list agent queue events: {}", e);
+            return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+        }
+    };
+
+    let report = build_agent_report(id, &events, window.from, window.to, window.interval);
+    Json(ApiResponse::success(report)).into_response()
+}
@@ -0,0 +1,103 @@
+//! Readiness endpoint and degraded-mode request guard
+
+use super::cdr_dto::ApiResponse;
+use super::user_handler::AppState;
+use crate::infrastructure::persistence::health::DbMode;
+use crate::infrastructure::protocols::sip::{OverloadLevel, OverloadStatus};
+use axum::{
+    extract::{Request, State},
+    http::{header, Method, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Response},
+    Json,
+};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use tracing::warn;
+
+/// Readiness report
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ReadinessResponse {
+    pub status: String,
+    pub database: DbMode,
+    pub degraded_since: Option<DateTime<Utc>>,
+    pub last_error: Option<String>,
+    /// SIP overload level and the load behind it
+    pub overload: Option<OverloadStatus>,
+}
+
+/// Readiness check
+///
+/// The server stays ready while degraded (calls and cached reads still work);
+/// the body reports the database mode and SIP overload level so operators
+/// can see the outage.
+pub async fn readiness_check(State(state): State<AppState>) -> Json<ApiResponse<ReadinessResponse>> {
+    let mut response = match &state.db_health {
+        Some(health) => ReadinessResponse {
+            status: if health.is_degraded() { "degraded" } else { "ready" }.to_string(),
+            database: health.mode(),
+            degraded_since: health.degraded_since(),
+            last_error: health.last_error(),
+            overload: None,
+        },
+        None => ReadinessResponse {
+            status: "ready".to_string(),
+            database: DbMode::Normal,
+            degraded_since: None,
+            last_error: None,
+            overload: None,
+        },
+    };
+
+    if let Some(overload) = &state.overload {
+        let status = overload.status();
+        if status.level != OverloadLevel::Normal {
+            response.status = "degraded".to_string();
+        }
+        response.overload = Some(status);
+    }
+
+    Json(ApiResponse::success(response))
+}
+
+/// Reject mutations while the database is degraded
+///
+/// Reads are let through: users and CDR lists are answered from the
+/// read-through caches, registrations from the registrar. A read with nothing
+/// cached still fails with a 500, reported as 503 so clients back off and retry.
+pub async fn degraded_mode_guard(
+    State(state): State<AppState>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let health = match &state.db_health {
+        Some(health) if health.is_degraded() => health.clone(),
+        _ => return next.run(request).await,
+    };
+
+    let retry_after = health.retry_after_secs().to_string();
+    let read_only = matches!(*request.method(), Method::GET | Method::HEAD | Method::OPTIONS);
+
+    if !read_only {
+        warn!(
+            "Rejecting {} {} while database is degraded",
+            request.method(),
+            request.uri().path()
+        );
+        return (
+            StatusCode::SERVICE_UNAVAILABLE,
+            [(header::RETRY_AFTER, retry_after)],
+            Json(ApiResponse::<()>::error(
+                "Database unavailable, server is in read-only mode".to_string(),
+            )),
+        )
+            .into_response();
+    }
+
+    let response = next.run(request).await;
+    if response.status() == StatusCode::INTERNAL_SERVER_ERROR {
+        return (StatusCode::SERVICE_UNAVAILABLE, [(header::RETRY_AFTER, retry_after)])
+            .into_response();
+    }
+    response
+}
@@ -0,0 +1,62 @@
+//! Hot standby replication API handlers
+
+use super::cdr_dto::ApiResponse;
+use super::user_handler::AppState;
+use crate::infrastructure::replication::{PromoteError, ReplicationNode};
+use axum::{
+    extract::State,
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    Json,
+};
+use std::sync::Arc;
+use tracing::{error, info, warn};
+
+#[allow(clippy::result_large_err)]
+fn replication(state: &AppState) -> Result<&Arc<ReplicationNode>, Response> {
+    state.replication.as_ref().ok_or_else(|| {
+        error!("Replication node not available");
+        (
+            StatusCode::SERVICE_UNAVAILABLE,
+            Json(ApiResponse::<()>::error(
+                "Replication not enabled".to_string(),
+            )),
+        )
+            .into_response()
+    })
+}
+
+/// Role, peer connection and replication lag of this node
+pub async fn get_replication_status(State(state): State<AppState>) -> Response {
+    let node = match replication(&state) {
+        Ok(node) => node,
+        Err(response) => return response,
+    };
+
+    Json(ApiResponse::success(node.status().await)).into_response()
+}
+
+/// Promote this standby node to active
+///
+/// Refused with 409 while the peer is still reachable and active.
+pub async fn promote_replication_node(State(state): State<AppState>) -> Response {
+    let node = match replication(&state) {
+        Ok(node) => node,
+        Err(response) => return response,
+    };
+
+    match node.promote().await {
+        Ok(()) => {
+            info!("API: Node promoted to active");
+            Json(ApiResponse::success(node.status().await)).into_response()
+        }
+        Err(e @ PromoteError::PeerActive(_)) => {
+            warn!("API: Promotion refused: {}", e);
+            (
+                StatusCode::CONFLICT,
+                Json(ApiResponse::<()>::error(e.to_string())),
+            )
+                .into_response()
+        }
+    }
+}
@@ -1,28 +1,114 @@
 //! API Router configuration
 
+use super::agent_state_handler::get_agent_state_history;
+use super::announcement_handler::{
+    create_announcement_route, delete_announcement_route, get_announcement_route,
+    list_announcement_routes, update_announcement_route,
+};
+use super::audio_handler::{
+    delete_audio, delete_moh_class, list_audio, list_moh_classes, put_moh_class, upload_audio, MAX_AUDIO_UPLOAD_BYTES,
+};
+use super::auto_attendant_handler::{
+    create_auto_attendant, delete_auto_attendant, get_auto_attendant, list_auto_attendants,
+    update_auto_attendant,
+};
+use super::branding_handler::{get_tenant_branding, update_tenant_branding};
+use super::call_debug_handler::{
+    enable_call_debug, enable_user_debug, get_call_debug, list_debug_targets,
+};
+use super::call_history_handler::{get_call_history, mark_call_history_read};
 use super::calls_handler::{get_active_call, get_active_calls, get_call_stats, hangup_call};
-use super::cdr_handler::{export_cdrs_csv, export_cdrs_json, get_cdr, get_cdr_by_call_id, list_cdrs};
+use super::cdr_handler::{
+    export_cdrs_csv, export_cdrs_json, get_cdr, get_cdr_by_call_id, get_cdr_stats,
+    get_suspect_calls, list_cdrs,
+};
+use super::transcription_handler::{get_transcription, start_transcription, stop_transcription};
+use super::transfer_handler::{consult_transfer, get_transfer, settle_transfer};
+use super::campaign_handler::{
+    cancel_campaign, create_campaign, get_campaign, list_campaigns, pause_campaign,
+    resume_campaign,
+};
+use super::class_of_service_handler::{
+    assign_user_class, clear_class_override, get_user_class, list_classes, set_class_override,
+};
+use super::config_report_handler::get_config_report;
 use super::conference_handler::{
-    create_conference_room, end_conference, get_conference_details, join_conference_room,
-    leave_conference_room, list_active_conferences, mute_conference_participant,
-    unmute_conference_participant,
+    create_conference_room, end_conference, get_conference_details, grant_floor,
+    join_conference_room, leave_conference_room, list_active_conferences,
+    mute_conference_participant, raise_hand, revoke_floor, unmute_conference_participant,
+};
+use super::device_handler::{get_device, get_provisioning_config, resync_device};
+use super::diagnostics_handler::download_diagnostics;
+use super::enrollment_handler::{
+    create_enrollment, list_user_devices, redeem_enrollment, revoke_user_device,
+};
+use super::directory_handler::{poly_directory, search_directory, yealink_directory};
+use super::feature_code_handler::list_feature_codes;
+use super::fraud_handler::{clear_fraud_suspension, get_fraud_status};
+use super::header_rules_handler::preview_header_rules;
+use super::idempotency::idempotency_guard;
+use super::lnp_handler::{flush_lnp_entry, get_lnp_entry};
+use super::maintenance_handler::{
+    get_tenant_maintenance, get_trunk_maintenance, list_maintenance, run_cdr_retention,
+    set_tenant_maintenance, set_trunk_maintenance,
+};
+use super::messages_handler::{
+    delete_message_history, get_message_history, get_unread_messages, mark_messages_read,
 };
 use super::metrics_handler::metrics_handler;
-use super::monitoring::{get_prometheus_metrics, get_system_health};
+use super::monitoring::{get_capacity, get_prometheus_metrics, get_setup_latency, get_system_health};
+use super::openapi::{get_openapi, swagger_ui};
+use super::privacy_handler::erase_subject;
+use super::queue_callback_handler::{cancel_queue_callback, list_queue_callbacks};
+use super::survey_handler::get_queue_survey_results;
+use super::queue_report_handler::{get_agent_report, get_queue_report};
+use super::readiness::{degraded_mode_guard, readiness_check};
+use super::replication_handler::{get_replication_status, promote_replication_node};
+use super::routing_lint_handler::lint_routing;
+use super::routing_simulation_handler::simulate_routing;
+use super::scheduled_call_handler::{
+    cancel_scheduled_call, cancel_user_scheduled_call, create_scheduled_call,
+    create_user_scheduled_call, delete_scheduled_call, get_scheduled_call,
+    list_scheduled_calls, list_user_scheduled_calls, update_scheduled_call,
+};
+use super::speed_dial_handler::{
+    create_company_speed_dial, create_user_speed_dial, delete_company_speed_dial,
+    delete_user_speed_dial, list_company_speed_dials, list_user_speed_dials,
+    update_company_speed_dial, update_user_speed_dial,
+};
+use super::storage_handler::get_storage_usage;
+use super::survivability_handler::get_survivability_status;
+use super::trunk_handler::{
+    get_trunk_registration, list_trunk_channels, list_trunk_registrations, reregister_trunk,
+};
 use super::user_handler::{
     change_password, create_user, delete_user, get_online_count, get_online_users, get_user,
-    get_user_by_username, get_user_registration_status, health_check, list_users, set_enabled,
-    update_user, AppState,
+    get_registration_history, get_user_by_username, get_user_registration_status, health_check,
+    list_users, set_enabled, update_user, AppState,
+};
+use super::voicemail_handler::{
+    delete_voicemail, forward_voicemail, get_voicemail_audio, list_user_voicemail,
+    update_voicemail,
 };
-use super::ws_handler::{ws_handler, EventBroadcaster};
+use super::voicemail_list_handler::{
+    create_voicemail_list, delete_voicemail_list, get_voicemail_list, list_voicemail_lists,
+    update_voicemail_list,
+};
+use super::ws_handler::{ws_handler, EventBroadcaster, WsState};
+use crate::infrastructure::telemetry;
 use axum::{
-    routing::{delete, get, post, put},
+    body::Body,
+    extract::DefaultBodyLimit,
+    http::Request,
+    middleware,
+    routing::{delete, get, patch, post, put},
     Router,
 };
 use metrics_exporter_prometheus::PrometheusHandle;
 use std::sync::Arc;
 use tower_http::cors::{Any, CorsLayer};
 use tower_http::trace::TraceLayer;
+use tracing::{info_span, Span};
 
 /// Build the API router
 pub fn build_router(
@@ -31,7 +117,9 @@ pub fn build_router(
     event_broadcaster: Arc<EventBroadcaster>,
 ) -> Router {
     // Health check route (no auth required)
-    let health_routes = Router::new().route("/health", get(health_check));
+    let health_routes = Router::new()
+        .route("/health", get(health_check))
+        .route("/readyz", get(readiness_check));
 
     // User management routes
     let user_routes = Router::new()
@@ -45,27 +133,265 @@ pub fn build_router(
         .route("/users/:id/enabled/:enabled", put(set_enabled))
         .route("/users/online", get(get_online_users))
         .route("/users/online/count", get(get_online_count))
-        .route("/users/:username/status", get(get_user_registration_status));
+        .route("/users/:username/status", get(get_user_registration_status))
+        .route("/users/:id/call-history", get(get_call_history))
+        .route("/users/:id/call-history/read", post(mark_call_history_read))
+        .route("/users/:id/registrations/history", get(get_registration_history))
+        .route("/users/:id/messages", get(get_message_history))
+        .route("/users/:id/messages", delete(delete_message_history))
+        .route("/users/:id/messages/unread", get(get_unread_messages))
+        .route("/users/:id/messages/read", post(mark_messages_read))
+        .route("/users/:id/voicemail", get(list_user_voicemail))
+        .route(
+            "/users/:id/voicemail/:message_id",
+            patch(update_voicemail).delete(delete_voicemail),
+        )
+        .route("/users/:id/voicemail/:message_id/audio", get(get_voicemail_audio))
+        .route("/users/:id/voicemail/:message_id/forward", post(forward_voicemail))
+        .route("/users/:id/fraud/status", get(get_fraud_status))
+        .route("/users/:id/fraud/clear", post(clear_fraud_suspension))
+        .route("/users/:id/debug", post(enable_user_debug))
+        .route("/users/:id/speed-dials", get(list_user_speed_dials))
+        .route("/users/:id/speed-dials", post(create_user_speed_dial))
+        .route("/users/:id/speed-dials/:speed_dial_id", put(update_user_speed_dial))
+        .route("/users/:id/speed-dials/:speed_dial_id", delete(delete_user_speed_dial));
+
+    // Company-wide speed dial routes
+    let speed_dial_routes = Router::new()
+        .route("/speed-dials", get(list_company_speed_dials))
+        .route("/speed-dials", post(create_company_speed_dial))
+        .route("/speed-dials/:id", put(update_company_speed_dial))
+        .route("/speed-dials/:id", delete(delete_company_speed_dial));
 
     // CDR routes
     let cdr_routes = Router::new()
         .route("/cdrs", get(list_cdrs))
+        .route("/cdrs/stats", get(get_cdr_stats))
+        .route("/cdrs/suspect", get(get_suspect_calls))
         .route("/cdrs/:id", get(get_cdr))
         .route("/cdrs/call-id/:call_id", get(get_cdr_by_call_id))
         .route("/cdrs/export/csv", get(export_cdrs_csv))
         .route("/cdrs/export/json", get(export_cdrs_json));
 
+    // Queue reporting routes
+    let queue_report_routes = Router::new()
+        .route("/queues/:id/reports", get(get_queue_report))
+        .route("/agents/:id/reports", get(get_agent_report));
+
+    // Queue callback routes
+    let queue_callback_routes = Router::new()
+        .route("/api/queues/:id/callbacks", get(list_queue_callbacks))
+        .route(
+            "/api/queues/:id/callbacks/:callback_id",
+            delete(cancel_queue_callback),
+        )
+        .route("/api/agents/:id/state-history", get(get_agent_state_history))
+        .route("/api/queues/:id/surveys/results", get(get_queue_survey_results));
+
     // Call management routes
     let call_routes = Router::new()
         .route("/calls", get(get_active_calls))
         .route("/calls/:call_id", get(get_active_call))
         .route("/calls/:call_id/hangup", post(hangup_call))
+        .route("/calls/:call_id/debug", get(get_call_debug).post(enable_call_debug))
         .route("/calls/stats", get(get_call_stats));
 
+    // Audio file management routes
+    let audio_routes = Router::new()
+        .route("/audio", post(upload_audio))
+        .route("/audio", get(list_audio))
+        .route("/audio/:id", delete(delete_audio))
+        .route("/audio/moh-classes", get(list_moh_classes))
+        .route("/audio/moh-classes/:name", put(put_moh_class).delete(delete_moh_class))
+        .layer(DefaultBodyLimit::max(MAX_AUDIO_UPLOAD_BYTES));
+
     // Monitoring routes
     let monitoring_routes = Router::new()
         .route("/monitoring/health", get(get_system_health))
-        .route("/monitoring/prometheus", get(get_prometheus_metrics));
+        .route("/monitoring/prometheus", get(get_prometheus_metrics))
+        .route("/api/monitoring/capacity", get(get_capacity))
+        .route("/api/monitoring/slo", get(get_setup_latency));
+
+    // Admin routes (credentials checked by the handlers)
+    let admin_routes = Router::new()
+        .route("/api/admin/diagnostics", get(download_diagnostics))
+        .route("/api/admin/maintenance/cdr-retention", post(run_cdr_retention))
+        .route("/api/admin/privacy/erasure", post(erase_subject))
+        .route("/api/admin/header-rules/preview", post(preview_header_rules))
+        .route("/api/admin/debug-targets", get(list_debug_targets))
+        .route("/api/admin/config-report", get(get_config_report))
+        .route("/api/admin/storage", get(get_storage_usage));
+
+    // OpenAPI document, and Swagger UI for admins when enabled
+    let docs_routes = Router::new()
+        .route("/api/openapi.json", get(get_openapi))
+        .route("/api/admin/docs", get(swagger_ui));
+
+    // Hot standby replication routes (outside the degraded mode guard:
+    // failover must work while the database is down)
+    let replication_routes = Router::new()
+        .route("/api/ha/status", get(get_replication_status))
+        .route("/api/ha/promote", post(promote_replication_node))
+        .with_state(state.clone());
+
+    // Branch survivability (outside the degraded mode guard: it matters
+    // most while the node is cut off)
+    let survivability_routes = Router::new()
+        .route("/api/survivability", get(get_survivability_status))
+        .with_state(state.clone());
+
+    // Upstream registration and channel usage of trunks
+    let trunk_routes = Router::new()
+        .route("/api/trunks/registrations", get(list_trunk_registrations))
+        .route("/api/trunks/channels", get(list_trunk_channels))
+        .route("/api/trunks/:id/registration", get(get_trunk_registration))
+        .route("/api/trunks/:id/register", post(reregister_trunk));
+
+    // Tenants and trunks taken out of service
+    let maintenance_routes = Router::new()
+        .route("/api/maintenance", get(list_maintenance))
+        .route(
+            "/api/tenants/:id/maintenance",
+            get(get_tenant_maintenance).put(set_tenant_maintenance),
+        )
+        .route(
+            "/api/trunks/:id/maintenance",
+            get(get_trunk_maintenance).put(set_trunk_maintenance),
+        );
+
+    // Announcement-only numbers
+    let announcement_routes = Router::new()
+        .route(
+            "/api/routing/announcements",
+            get(list_announcement_routes).post(create_announcement_route),
+        )
+        .route(
+            "/api/routing/announcements/:id",
+            get(get_announcement_route)
+                .put(update_announcement_route)
+                .delete(delete_announcement_route),
+        );
+
+    // Auto-attendants
+    let auto_attendant_routes = Router::new()
+        .route(
+            "/api/routing/auto-attendants",
+            get(list_auto_attendants).post(create_auto_attendant),
+        )
+        .route(
+            "/api/routing/auto-attendants/:id",
+            get(get_auto_attendant)
+                .put(update_auto_attendant)
+                .delete(delete_auto_attendant),
+        );
+
+    // Voicemail distribution lists
+    let voicemail_list_routes = Router::new()
+        .route(
+            "/api/voicemail/lists",
+            get(list_voicemail_lists).post(create_voicemail_list),
+        )
+        .route(
+            "/api/voicemail/lists/:id",
+            get(get_voicemail_list)
+                .put(update_voicemail_list)
+                .delete(delete_voicemail_list),
+        );
+
+    // Number portability cache, routing simulation and lint (credentials
+    // checked by the handlers)
+    let lnp_routes = Router::new()
+        .route(
+            "/api/routing/lnp/:number",
+            get(get_lnp_entry).delete(flush_lnp_entry),
+        )
+        .route("/api/routing/simulate", post(simulate_routing))
+        .route("/api/routing/lint", post(lint_routing));
+
+    // Classes of service and the classes of users
+    let class_of_service_routes = Router::new()
+        .route("/api/classes-of-service", get(list_classes))
+        .route(
+            "/api/users/:id/class-of-service",
+            get(get_user_class).put(assign_user_class),
+        )
+        .route(
+            "/api/users/:id/class-of-service/override",
+            put(set_class_override).delete(clear_class_override),
+        );
+
+    // Notification campaigns
+    let campaign_routes = Router::new()
+        .route("/api/campaigns", get(list_campaigns).post(create_campaign))
+        .route("/api/campaigns/:id", get(get_campaign))
+        .route("/api/campaigns/:id/pause", post(pause_campaign))
+        .route("/api/campaigns/:id/resume", post(resume_campaign))
+        .route("/api/campaigns/:id/cancel", post(cancel_campaign));
+
+    // Wake-up calls and reminders, and those of a user
+    let scheduled_call_routes = Router::new()
+        .route(
+            "/api/scheduled-calls",
+            get(list_scheduled_calls).post(create_scheduled_call),
+        )
+        .route(
+            "/api/scheduled-calls/:id",
+            get(get_scheduled_call)
+                .put(update_scheduled_call)
+                .delete(delete_scheduled_call),
+        )
+        .route("/api/scheduled-calls/:id/cancel", post(cancel_scheduled_call))
+        .route(
+            "/api/users/:id/scheduled-calls",
+            get(list_user_scheduled_calls).post(create_user_scheduled_call),
+        )
+        .route(
+            "/api/users/:id/scheduled-calls/:call_id",
+            delete(cancel_user_scheduled_call),
+        );
+
+    // Live captions of a call in progress
+    let transcription_routes = Router::new()
+        .route("/api/calls/:call_id/transcription", get(get_transcription))
+        .route("/api/calls/:call_id/transcription/start", post(start_transcription))
+        .route("/api/calls/:call_id/transcription/stop", post(stop_transcription));
+
+    // Warm transfers of a call in progress
+    let transfer_routes = Router::new()
+        .route("/api/calls/:call_id/transfer", get(get_transfer))
+        .route("/api/calls/:call_id/transfer/consult", post(consult_transfer))
+        .route("/api/calls/:call_id/transfer/:action", post(settle_transfer));
+
+    // Per-tenant MOH, prompts and outbound caller identity
+    let branding_routes = Router::new().route(
+        "/api/tenants/:id/branding",
+        get(get_tenant_branding).patch(update_tenant_branding),
+    );
+
+    // Phone directory routes (device tokens checked by the handlers)
+    let directory_routes = Router::new()
+        .route("/api/directory", get(search_directory))
+        .route("/directory/yealink.xml", get(yealink_directory))
+        .route("/directory/poly.xml", get(poly_directory));
+
+    // Provisioned phones: configuration (device tokens checked by the
+    // handler) and resync
+    let device_routes = Router::new()
+        .route("/provisioning/:mac", get(get_provisioning_config))
+        .route("/api/devices/:mac", get(get_device))
+        .route("/api/devices/:mac/resync", post(resync_device));
+
+    // Softphone enrollment (the link itself needs no credentials) and the
+    // enrolled devices of users
+    let enrollment_routes = Router::new()
+        .route("/api/users/:id/enrollments", post(create_enrollment))
+        .route("/enroll/:token", get(redeem_enrollment))
+        .route("/api/users/:id/devices", get(list_user_devices))
+        .route("/api/users/:id/devices/:device_id", delete(revoke_user_device));
+
+    // Star codes in effect per tenant
+    let feature_code_routes =
+        Router::new().route("/api/feature-codes", get(list_feature_codes));
 
     // Conference routes
     let conference_routes = Router::new()
@@ -76,27 +402,61 @@ pub fn build_router(
         .route("/conferences/:room_id/end", post(end_conference))
         .route("/conferences/leave", post(leave_conference_room))
         .route("/conferences/participants/mute", post(mute_conference_participant))
-        .route("/conferences/participants/unmute", post(unmute_conference_participant));
+        .route("/conferences/participants/unmute", post(unmute_conference_participant))
+        .route("/conferences/participants/hand", post(raise_hand))
+        .route(
+            "/conferences/:room_id/participants/:participant_id/floor",
+            post(grant_floor).delete(revoke_floor),
+        );
 
     // Metrics route (separate state)
     let metrics_routes = Router::new()
         .route("/metrics", get(metrics_handler))
         .with_state(prometheus_handle);
 
-    // WebSocket route (separate state)
+    // WebSocket route: events, and call control for authenticated clients
     let ws_routes = Router::new()
         .route("/ws", get(ws_handler))
-        .with_state(event_broadcaster);
+        .with_state(WsState::new(event_broadcaster, state.clone()));
+
+    // Retried call control and provisioning requests run once per
+    // Idempotency-Key
+    let idempotent = middleware::from_fn_with_state(state.clone(), idempotency_guard);
 
     // Combine routes with state
     Router::new()
         .merge(health_routes)
-        .merge(user_routes)
+        .merge(user_routes.route_layer(idempotent.clone()))
         .merge(cdr_routes)
-        .merge(call_routes)
+        .merge(call_routes.route_layer(idempotent.clone()))
         .merge(monitoring_routes)
-        .merge(conference_routes)
+        .merge(conference_routes.route_layer(idempotent.clone()))
+        .merge(audio_routes)
+        .merge(speed_dial_routes.route_layer(idempotent.clone()))
+        .merge(queue_report_routes)
+        .merge(queue_callback_routes)
+        .merge(admin_routes)
+        .merge(docs_routes)
+        .merge(directory_routes)
+        .merge(device_routes.route_layer(idempotent.clone()))
+        .merge(enrollment_routes)
+        .merge(feature_code_routes)
+        .merge(trunk_routes.route_layer(idempotent.clone()))
+        .merge(branding_routes.route_layer(idempotent.clone()))
+        .merge(maintenance_routes.route_layer(idempotent.clone()))
+        .merge(announcement_routes)
+        .merge(auto_attendant_routes)
+        .merge(voicemail_list_routes)
+        .merge(lnp_routes)
+        .merge(class_of_service_routes)
+        .merge(campaign_routes.route_layer(idempotent.clone()))
+        .merge(scheduled_call_routes.route_layer(idempotent.clone()))
+        .merge(transcription_routes.route_layer(idempotent.clone()))
+        .merge(transfer_routes.route_layer(idempotent.clone()))
+        .layer(middleware::from_fn_with_state(state.clone(), degraded_mode_guard))
         .with_state(state)
+        .merge(replication_routes)
+        .merge(survivability_routes)
         .merge(metrics_routes)
         .merge(ws_routes)
         .layer(
@@ -105,7 +465,23 @@ pub fn build_router(
                 .allow_methods(Any)
                 .allow_headers(Any),
         )
-        .layer(TraceLayer::new_for_http())
+        .layer(TraceLayer::new_for_http().make_span_with(request_span))
+}
+
+/// Span of an API request, continuing the caller's trace when it sent a
+/// `traceparent` header
+pub(crate) fn request_span(request: &Request<Body>) -> Span {
+    let span = info_span!(
+        "request",
+        otel.name = %format!("{} {}", request.method(), request.uri().path()),
+        method = %request.method(),
+        uri = %request.uri(),
+    );
+    let header = |name| request.headers().get(name).and_then(|v| v.to_str().ok());
+    if let Some(traceparent) = header("traceparent") {
+        telemetry::set_remote_parent(&span, traceparent, header("tracestate"));
+    }
+    span
 }
 
 #[cfg(test)]
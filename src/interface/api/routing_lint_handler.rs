@@ -0,0 +1,51 @@
+//! Routing lint API handler
+//!
+//! `POST /api/routing/lint` cross-checks the dial plan and the routing
+//! configuration as it is now: shadowed rules, feature codes overlapping
+//! extensions, references to missing extensions, audio files, queues and
+//! trunks, and forwarding loops. Credentials are checked as for
+//! diagnostics.
+
+use super::cdr_dto::ApiResponse;
+use super::diagnostics_handler::{authorize, client_ip};
+use super::user_handler::AppState;
+use crate::domain::routing::LintSeverity;
+use axum::{
+    extract::State,
+    http::{HeaderMap, StatusCode},
+    response::{IntoResponse, Response},
+    Json,
+};
+use tracing::{error, info};
+
+fn unavailable(what: &str) -> Response {
+    error!("{} not available", what);
+    (
+        StatusCode::SERVICE_UNAVAILABLE,
+        Json(ApiResponse::<()>::error(format!("{} not available", what))),
+    )
+        .into_response()
+}
+
+/// Findings of the routing lint (requires `system:config`)
+pub async fn lint_routing(State(state): State<AppState>, headers: HeaderMap) -> Response {
+    let Some(diagnostics) = state.diagnostics.clone() else {
+        return unavailable("Admin authentication");
+    };
+    let Some(linter) = state.routing_linter.clone() else {
+        return unavailable("Routing lint");
+    };
+    let ip = client_ip(&headers);
+    if let Err(response) = authorize(&state, &diagnostics, &headers, &ip, "routing", "lint").await {
+        return response;
+    }
+
+    let report = linter.lint().await;
+    info!(
+        "API: Routing lint of {} rules: {} errors, {} warnings",
+        report.rules,
+        report.count(LintSeverity::Error),
+        report.count(LintSeverity::Warning)
+    );
+    Json(ApiResponse::success(report)).into_response()
+}
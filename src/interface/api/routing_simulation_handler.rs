@@ -0,0 +1,124 @@
+//! Routing simulation API handler
+//!
+//! `POST /api/routing/simulate` runs the routing chain of the INVITE path
+//! for a call that is never placed: the trace names every step that
+//! matched, the rule it applied, how it rewrote the dialed URI and how
+//! routing ended. Credentials are checked as for diagnostics.
+
+use super::cdr_dto::ApiResponse;
+use super::diagnostics_handler::{authorize, client_ip};
+use super::user_handler::AppState;
+use crate::domain::routing::{RouteTrace, RoutedCall};
+use axum::{
+    extract::State,
+    http::{HeaderMap, StatusCode},
+    response::{IntoResponse, Response},
+    Json,
+};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::net::IpAddr;
+use tracing::{error, info};
+
+#[derive(Debug, Deserialize)]
+pub struct SimulateRequest {
+    /// Calling extension or SIP URI
+    pub caller: String,
+    /// Digits or SIP URI as dialed
+    pub dialed: String,
+    /// When the call is placed; now by default
+    #[serde(default)]
+    pub at: Option<DateTime<Utc>>,
+    /// Realm of the caller and of dialed digits without one
+    #[serde(default)]
+    pub tenant: Option<String>,
+    /// Address the INVITE comes from, for calls from a trunk
+    #[serde(default)]
+    pub source: Option<IpAddr>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct SimulateResponse {
+    pub caller_uri: String,
+    pub dialed_uri: String,
+    pub at: DateTime<Utc>,
+    #[serde(flatten)]
+    pub trace: RouteTrace,
+}
+
+fn unavailable(what: &str) -> Response {
+    error!("{} not available", what);
+    (
+        StatusCode::SERVICE_UNAVAILABLE,
+        Json(ApiResponse::<()>::error(format!("{} not available", what))),
+    )
+        .into_response()
+}
+
+fn bad_request(message: String) -> Response {
+    (StatusCode::BAD_REQUEST, Json(ApiResponse::<()>::error(message))).into_response()
+}
+
+/// `value` as a SIP URI, in `tenant` when it has no host
+fn sip_uri(value: &str, tenant: Option<&str>) -> Option<String> {
+    let value = value.trim();
+    if value.is_empty() {
+        return None;
+    }
+    if value.contains('@') {
+        return Some(if value.starts_with("sip:") || value.starts_with("sips:") {
+            value.to_string()
+        } else {
+            format!("sip:{}", value)
+        });
+    }
+    tenant.map(|tenant| format!("sip:{}@{}", value, tenant))
+}
+
+/// Trace of a simulated call (requires `system:config`)
+pub async fn simulate_routing(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Json(request): Json<SimulateRequest>,
+) -> Response {
+    let Some(diagnostics) = state.diagnostics.clone() else {
+        return unavailable("Admin authentication");
+    };
+    let Some(simulator) = state.routing_simulator.clone() else {
+        return unavailable("Routing simulation");
+    };
+    let ip = client_ip(&headers);
+    if let Err(response) =
+        authorize(&state, &diagnostics, &headers, &ip, "routing", "simulate").await
+    {
+        return response;
+    }
+
+    let tenant = request.tenant.as_deref();
+    let Some(caller_uri) = sip_uri(&request.caller, tenant) else {
+        return bad_request("caller needs a tenant or a SIP URI".to_string());
+    };
+    let Some(dialed_uri) = sip_uri(&request.dialed, tenant) else {
+        return bad_request("dialed needs a tenant or a SIP URI".to_string());
+    };
+
+    let at = request.at.unwrap_or_else(Utc::now);
+    let mut call = RoutedCall::new(caller_uri.clone(), dialed_uri.clone(), at);
+    call.source = request.source;
+    let trace = simulator.simulate(call).await;
+    info!(
+        "API: Simulated call {} -> {} at {}: {} steps",
+        caller_uri,
+        dialed_uri,
+        at,
+        trace.steps.len()
+    );
+
+    Json(ApiResponse::success(SimulateResponse {
+        caller_uri,
+        dialed_uri,
+        at,
+        trace,
+    }))
+    .into_response()
+}
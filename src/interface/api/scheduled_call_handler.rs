@@ -0,0 +1,218 @@
+//! Scheduled call API handlers
+//!
+//! `/api/scheduled-calls` creates, lists, changes and removes wake-up calls
+//! and reminders; `POST /api/scheduled-calls/:id/cancel` stops one while
+//! keeping its history. Users manage their own wake-up calls under
+//! `/api/users/:id/scheduled-calls` with their HTTP Basic credentials.
+
+use super::cdr_dto::ApiResponse;
+use super::enrollment_handler::authorize_for_user;
+use super::user_handler::AppState;
+use crate::application::scheduled_call::ScheduledCallService;
+use crate::domain::scheduled_call::{CreateScheduledCall, ScheduledCallError};
+use crate::domain::user::User;
+use axum::{
+    extract::{Path, State},
+    http::{HeaderMap, StatusCode},
+    response::{IntoResponse, Response},
+    Json,
+};
+use serde::Deserialize;
+use std::sync::Arc;
+use tracing::{error, warn};
+use uuid::Uuid;
+
+/// A scheduled call and the tenant it belongs to
+#[derive(Debug, Deserialize)]
+pub struct CreateScheduledCallRequest {
+    #[serde(flatten)]
+    pub call: CreateScheduledCall,
+    /// Tenant realm whose time zone applies
+    #[serde(default)]
+    pub realm: Option<String>,
+}
+
+#[allow(clippy::result_large_err)]
+fn service(state: &AppState) -> Result<&Arc<ScheduledCallService>, Response> {
+    state.scheduled_calls.as_ref().ok_or_else(|| {
+        error!("Scheduled call service not available");
+        (
+            StatusCode::SERVICE_UNAVAILABLE,
+            Json(ApiResponse::<()>::error("Scheduled calls not enabled".to_string())),
+        )
+            .into_response()
+    })
+}
+
+fn refused(e: ScheduledCallError) -> Response {
+    let status = match e {
+        ScheduledCallError::NotFound(_) => StatusCode::NOT_FOUND,
+        ScheduledCallError::Invalid(_) => StatusCode::BAD_REQUEST,
+        ScheduledCallError::InvalidState(_) => StatusCode::CONFLICT,
+        ScheduledCallError::Repository(_) => StatusCode::INTERNAL_SERVER_ERROR,
+    };
+    warn!("API: Scheduled call request refused: {}", e);
+    (status, Json(ApiResponse::<()>::error(e.to_string()))).into_response()
+}
+
+/// Schedule a call
+pub async fn create_scheduled_call(
+    State(state): State<AppState>,
+    Json(request): Json<CreateScheduledCallRequest>,
+) -> Response {
+    let service = match service(&state) {
+        Ok(service) => service,
+        Err(response) => return response,
+    };
+    match service.create(request.call, request.realm.as_deref(), "api").await {
+        Ok(call) => (StatusCode::CREATED, Json(ApiResponse::success(call))).into_response(),
+        Err(e) => refused(e),
+    }
+}
+
+/// Every schedule, soonest first
+pub async fn list_scheduled_calls(State(state): State<AppState>) -> Response {
+    let service = match service(&state) {
+        Ok(service) => service,
+        Err(response) => return response,
+    };
+    match service.list().await {
+        Ok(calls) => Json(ApiResponse::success(calls)).into_response(),
+        Err(e) => refused(e),
+    }
+}
+
+/// A schedule and the outcome of its last call
+pub async fn get_scheduled_call(State(state): State<AppState>, Path(id): Path<Uuid>) -> Response {
+    let service = match service(&state) {
+        Ok(service) => service,
+        Err(response) => return response,
+    };
+    match service.get(id).await {
+        Ok(call) => Json(ApiResponse::success(call)).into_response(),
+        Err(e) => refused(e),
+    }
+}
+
+/// Change the time, recurrence or content of a pending schedule
+pub async fn update_scheduled_call(
+    State(state): State<AppState>,
+    Path(id): Path<Uuid>,
+    Json(request): Json<CreateScheduledCall>,
+) -> Response {
+    let service = match service(&state) {
+        Ok(service) => service,
+        Err(response) => return response,
+    };
+    match service.update(id, request).await {
+        Ok(call) => Json(ApiResponse::success(call)).into_response(),
+        Err(e) => refused(e),
+    }
+}
+
+/// Stop a pending schedule; it is kept with its history
+pub async fn cancel_scheduled_call(State(state): State<AppState>, Path(id): Path<Uuid>) -> Response {
+    let service = match service(&state) {
+        Ok(service) => service,
+        Err(response) => return response,
+    };
+    match service.cancel(id).await {
+        Ok(call) => Json(ApiResponse::success(call)).into_response(),
+        Err(e) => refused(e),
+    }
+}
+
+pub async fn delete_scheduled_call(State(state): State<AppState>, Path(id): Path<Uuid>) -> Response {
+    let service = match service(&state) {
+        Ok(service) => service,
+        Err(response) => return response,
+    };
+    match service.delete(id).await {
+        Ok(()) => StatusCode::NO_CONTENT.into_response(),
+        Err(e) => refused(e),
+    }
+}
+
+/// The user, once the caller may manage their scheduled calls
+async fn user_for(state: &AppState, headers: &HeaderMap, id: i32) -> Result<User, Response> {
+    authorize_for_user(state, headers, id).await?;
+    match state.user_repository.find_by_id(id).await {
+        Ok(Some(user)) => Ok(user),
+        Ok(None) => Err((
+            StatusCode::NOT_FOUND,
+            Json(ApiResponse::<()>::error(format!("User {} not found", id))),
+        )
+            .into_response()),
+        Err(e) => {
+            error!("API: Failed to get user: {}", e);
+            Err(StatusCode::INTERNAL_SERVER_ERROR.into_response())
+        }
+    }
+}
+
+/// Scheduled calls to or made by a user
+pub async fn list_user_scheduled_calls(
+    State(state): State<AppState>,
+    Path(id): Path<i32>,
+    headers: HeaderMap,
+) -> Response {
+    let service = match service(&state) {
+        Ok(service) => service,
+        Err(response) => return response,
+    };
+    let user = match user_for(&state, &headers, id).await {
+        Ok(user) => user,
+        Err(response) => return response,
+    };
+    match service.list_for_user(&user.username).await {
+        Ok(calls) => Json(ApiResponse::success(calls)).into_response(),
+        Err(e) => refused(e),
+    }
+}
+
+/// Schedule a wake-up call to the user's own extension
+pub async fn create_user_scheduled_call(
+    State(state): State<AppState>,
+    Path(id): Path<i32>,
+    headers: HeaderMap,
+    Json(mut request): Json<CreateScheduledCall>,
+) -> Response {
+    let service = match service(&state) {
+        Ok(service) => service,
+        Err(response) => return response,
+    };
+    let user = match user_for(&state, &headers, id).await {
+        Ok(user) => user,
+        Err(response) => return response,
+    };
+    request.target = user.username.clone();
+    match service.create(request, Some(&user.realm), &user.username).await {
+        Ok(call) => (StatusCode::CREATED, Json(ApiResponse::success(call))).into_response(),
+        Err(e) => refused(e),
+    }
+}
+
+/// Cancel one of the user's scheduled calls
+pub async fn cancel_user_scheduled_call(
+    State(state): State<AppState>,
+    Path((id, call_id)): Path<(i32, Uuid)>,
+    headers: HeaderMap,
+) -> Response {
+    let service = match service(&state) {
+        Ok(service) => service,
+        Err(response) => return response,
+    };
+    let user = match user_for(&state, &headers, id).await {
+        Ok(user) => user,
+        Err(response) => return response,
+    };
+    match service.get(call_id).await {
+        Ok(call) if call.target == user.username || call.created_by == user.username => {}
+        Ok(_) => return refused(ScheduledCallError::NotFound(call_id)),
+        Err(e) => return refused(e),
+    }
+    match service.cancel(call_id).await {
+        Ok(call) => Json(ApiResponse::success(call)).into_response(),
+        Err(e) => refused(e),
+    }
+}
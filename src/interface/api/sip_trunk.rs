@@ -1,4 +1,5 @@
 /// SIP Trunk management REST API
+use crate::domain::shared::SecretBox;
 use crate::domain::sip_trunk::{SipTrunk, SipTrunkRepository, TrunkDirection, TrunkType};
 use axum::{
     extract::{Path, State},
@@ -41,7 +42,7 @@ struct CreateTrunkRequest {
     sip_port: Option<u16>,
     direction: Option<String>,
     username: Option<String>,
-    password: Option<String>,
+    password: Option<SecretBox>,
 }
 
 /// Request to update a SIP trunk
@@ -51,7 +52,7 @@ struct UpdateTrunkRequest {
     sip_server: Option<String>,
     sip_port: Option<u16>,
     username: Option<String>,
-    password: Option<String>,
+    password: Option<SecretBox>,
     enabled: Option<bool>,
 }
 
@@ -145,7 +146,7 @@ async fn create_trunk(
 
     if let Some(username) = req.username {
         if let Some(password) = req.password {
-            trunk = trunk.with_credentials(username, password);
+            trunk = trunk.with_credentials(username, password.expose().to_string());
         }
     }
 
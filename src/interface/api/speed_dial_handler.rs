@@ -0,0 +1,301 @@
+//! Speed dial API handlers
+//!
+//! Personal speed dials live under `/users/:id/speed-dials`; company-wide
+//! short codes managed by admins live under `/speed-dials`. Changes resync
+//! the affected phones, which show speed dials on their line keys.
+
+use super::cdr_dto::ApiResponse;
+use super::device_handler::resync_after;
+use super::user_handler::AppState;
+use crate::domain::speed_dial::{
+    effective_speed_dials, SpeedDial, SpeedDialError, SpeedDialRepository,
+};
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    Json,
+};
+use chrono::Utc;
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+use tracing::{error, info};
+use uuid::Uuid;
+
+/// Create/update speed dial request
+#[derive(Debug, Deserialize)]
+pub struct SpeedDialRequest {
+    pub code: String,
+    pub target: String,
+    pub label: Option<String>,
+}
+
+/// Speed dials of a user
+#[derive(Debug, Serialize, Deserialize)]
+pub struct UserSpeedDialsResponse {
+    /// The user's own entries
+    pub personal: Vec<SpeedDial>,
+    /// Personal entries merged over company-wide codes, sorted by code
+    pub effective: Vec<SpeedDial>,
+}
+
+fn repository_unavailable() -> Response {
+    error!("Speed dial repository not available");
+    (
+        StatusCode::SERVICE_UNAVAILABLE,
+        Json(ApiResponse::<()>::error("Speed dials not available".to_string())),
+    )
+        .into_response()
+}
+
+fn database_error(e: String) -> Response {
+    error!("API: Speed dial database error: {}", e);
+    StatusCode::INTERNAL_SERVER_ERROR.into_response()
+}
+
+fn not_found(id: Uuid) -> Response {
+    (
+        StatusCode::NOT_FOUND,
+        Json(ApiResponse::<()>::error(format!("Speed dial {} not found", id))),
+    )
+        .into_response()
+}
+
+fn validation_error(e: SpeedDialError) -> Response {
+    let status = match e {
+        SpeedDialError::Duplicate(_) => StatusCode::CONFLICT,
+        _ => StatusCode::BAD_REQUEST,
+    };
+    (status, Json(ApiResponse::<()>::error(e.to_string()))).into_response()
+}
+
+async fn list_scope(
+    repo: &Arc<dyn SpeedDialRepository>,
+    user_id: Option<i32>,
+) -> Result<Vec<SpeedDial>, String> {
+    match user_id {
+        Some(user_id) => repo.list_for_user(user_id).await,
+        None => repo.list_company().await,
+    }
+}
+
+/// Validate and store a new or updated speed dial in its scope
+async fn save(
+    repo: &Arc<dyn SpeedDialRepository>,
+    speed_dial: SpeedDial,
+    is_new: bool,
+) -> Response {
+    if let Err(e) = speed_dial.validate() {
+        return validation_error(e);
+    }
+
+    let existing = match list_scope(repo, speed_dial.user_id).await {
+        Ok(existing) => existing,
+        Err(e) => return database_error(e),
+    };
+    if existing
+        .iter()
+        .any(|s| s.code == speed_dial.code && s.id != speed_dial.id)
+    {
+        return validation_error(SpeedDialError::Duplicate(speed_dial.code));
+    }
+
+    let result = if is_new {
+        repo.create(&speed_dial).await
+    } else {
+        repo.update(&speed_dial).await
+    };
+
+    match result {
+        Ok(()) => {
+            let status = if is_new { StatusCode::CREATED } else { StatusCode::OK };
+            (status, Json(ApiResponse::success(speed_dial))).into_response()
+        }
+        Err(e) => database_error(e),
+    }
+}
+
+/// Load a speed dial and check it belongs to the given scope
+async fn load_in_scope(
+    repo: &Arc<dyn SpeedDialRepository>,
+    id: Uuid,
+    user_id: Option<i32>,
+) -> Result<SpeedDial, Response> {
+    match repo.get_by_id(id).await {
+        Ok(Some(speed_dial)) if speed_dial.user_id == user_id => Ok(speed_dial),
+        Ok(_) => Err(not_found(id)),
+        Err(e) => Err(database_error(e)),
+    }
+}
+
+async fn update_in_scope(
+    repo: &Arc<dyn SpeedDialRepository>,
+    id: Uuid,
+    user_id: Option<i32>,
+    req: SpeedDialRequest,
+) -> Response {
+    let mut speed_dial = match load_in_scope(repo, id, user_id).await {
+        Ok(speed_dial) => speed_dial,
+        Err(response) => return response,
+    };
+    speed_dial.code = req.code;
+    speed_dial.target = req.target;
+    speed_dial.label = req.label;
+    speed_dial.updated_at = Utc::now();
+
+    save(repo, speed_dial, false).await
+}
+
+async fn delete_in_scope(
+    repo: &Arc<dyn SpeedDialRepository>,
+    id: Uuid,
+    user_id: Option<i32>,
+) -> Response {
+    if let Err(response) = load_in_scope(repo, id, user_id).await {
+        return response;
+    }
+    match repo.delete(id).await {
+        Ok(()) => StatusCode::NO_CONTENT.into_response(),
+        Err(e) => database_error(e),
+    }
+}
+
+/// Check that the user exists
+async fn ensure_user(state: &AppState, id: i32) -> Result<(), Response> {
+    match state.user_repository.find_by_id(id).await {
+        Ok(Some(_)) => Ok(()),
+        Ok(None) => Err((
+            StatusCode::NOT_FOUND,
+            Json(ApiResponse::<()>::error(format!("User {} not found", id))),
+        )
+            .into_response()),
+        Err(e) => {
+            error!("API: Failed to get user: {}", e);
+            Err(StatusCode::INTERNAL_SERVER_ERROR.into_response())
+        }
+    }
+}
+
+/// List a user's speed dials and the effective set including company codes
+pub async fn list_user_speed_dials(
+    State(state): State<AppState>,
+    Path(id): Path<i32>,
+) -> Response {
+    let repo = match &state.speed_dial_repository {
+        Some(repo) => repo,
+        None => return repository_unavailable(),
+    };
+    if let Err(response) = ensure_user(&state, id).await {
+        return response;
+    }
+
+    let personal = match repo.list_for_user(id).await {
+        Ok(personal) => personal,
+        Err(e) => return database_error(e),
+    };
+    let company = match repo.list_company().await {
+        Ok(company) => company,
+        Err(e) => return database_error(e),
+    };
+    let effective = effective_speed_dials(&personal, &company);
+
+    Json(ApiResponse::success(UserSpeedDialsResponse { personal, effective })).into_response()
+}
+
+/// Create a personal speed dial
+pub async fn create_user_speed_dial(
+    State(state): State<AppState>,
+    Path(id): Path<i32>,
+    Json(req): Json<SpeedDialRequest>,
+) -> Response {
+    let repo = match &state.speed_dial_repository {
+        Some(repo) => repo,
+        None => return repository_unavailable(),
+    };
+    if let Err(response) = ensure_user(&state, id).await {
+        return response;
+    }
+
+    info!("API: Creating speed dial {} for user {}", req.code, id);
+    let speed_dial = SpeedDial::new(Some(id), req.code, req.target, req.label);
+    let response = save(repo, speed_dial, true).await;
+    resync_after(&state, Some(id), response).await
+}
+
+/// Update a personal speed dial
+pub async fn update_user_speed_dial(
+    State(state): State<AppState>,
+    Path((id, speed_dial_id)): Path<(i32, Uuid)>,
+    Json(req): Json<SpeedDialRequest>,
+) -> Response {
+    let response = match &state.speed_dial_repository {
+        Some(repo) => update_in_scope(repo, speed_dial_id, Some(id), req).await,
+        None => return repository_unavailable(),
+    };
+    resync_after(&state, Some(id), response).await
+}
+
+/// Delete a personal speed dial
+pub async fn delete_user_speed_dial(
+    State(state): State<AppState>,
+    Path((id, speed_dial_id)): Path<(i32, Uuid)>,
+) -> Response {
+    let response = match &state.speed_dial_repository {
+        Some(repo) => delete_in_scope(repo, speed_dial_id, Some(id)).await,
+        None => return repository_unavailable(),
+    };
+    resync_after(&state, Some(id), response).await
+}
+
+/// List company-wide short codes
+pub async fn list_company_speed_dials(State(state): State<AppState>) -> Response {
+    let repo = match &state.speed_dial_repository {
+        Some(repo) => repo,
+        None => return repository_unavailable(),
+    };
+    match repo.list_company().await {
+        Ok(company) => Json(ApiResponse::success(company)).into_response(),
+        Err(e) => database_error(e),
+    }
+}
+
+/// Create a company-wide short code
+pub async fn create_company_speed_dial(
+    State(state): State<AppState>,
+    Json(req): Json<SpeedDialRequest>,
+) -> Response {
+    let repo = match &state.speed_dial_repository {
+        Some(repo) => repo,
+        None => return repository_unavailable(),
+    };
+
+    info!("API: Creating company speed dial {}", req.code);
+    let speed_dial = SpeedDial::new(None, req.code, req.target, req.label);
+    let response = save(repo, speed_dial, true).await;
+    resync_after(&state, None, response).await
+}
+
+/// Update a company-wide short code
+pub async fn update_company_speed_dial(
+    State(state): State<AppState>,
+    Path(id): Path<Uuid>,
+    Json(req): Json<SpeedDialRequest>,
+) -> Response {
+    let response = match &state.speed_dial_repository {
+        Some(repo) => update_in_scope(repo, id, None, req).await,
+        None => return repository_unavailable(),
+    };
+    resync_after(&state, None, response).await
+}
+
+/// Delete a company-wide short code
+pub async fn delete_company_speed_dial(
+    State(state): State<AppState>,
+    Path(id): Path<Uuid>,
+) -> Response {
+    let response = match &state.speed_dial_repository {
+        Some(repo) => delete_in_scope(repo, id, None).await,
+        None => return repository_unavailable(),
+    };
+    resync_after(&state, None, response).await
+}
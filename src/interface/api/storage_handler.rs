@@ -0,0 +1,50 @@
+//! Storage usage API handler
+//!
+//! `GET /api/admin/storage` returns the space used by recordings,
+//! voicemail, captures and archives against their quotas, the free space
+//! of their filesystems and the warning threshold each one is at.
+//! Credentials are checked as for diagnostics.
+
+use super::cdr_dto::ApiResponse;
+use super::diagnostics_handler::{authorize, client_ip};
+use super::user_handler::AppState;
+use axum::{
+    extract::State,
+    http::{HeaderMap, StatusCode},
+    response::{IntoResponse, Response},
+    Json,
+};
+use tracing::error;
+
+fn unavailable(what: &str) -> Response {
+    error!("{} not available", what);
+    (
+        StatusCode::SERVICE_UNAVAILABLE,
+        Json(ApiResponse::<()>::error(format!("{} not available", what))),
+    )
+        .into_response()
+}
+
+/// Current usage per category (requires `system:config`)
+pub async fn get_storage_usage(State(state): State<AppState>, headers: HeaderMap) -> Response {
+    let Some(diagnostics) = state.diagnostics.clone() else {
+        return unavailable("Admin authentication");
+    };
+    let Some(storage) = state.storage.clone() else {
+        return unavailable("Storage guard");
+    };
+
+    let ip = client_ip(&headers);
+    if let Err(response) = authorize(&state, &diagnostics, &headers, &ip, "storage", "get").await {
+        return response;
+    }
+
+    // Measuring walks the directories
+    match tokio::task::spawn_blocking(move || storage.usage()).await {
+        Ok(usage) => Json(ApiResponse::success(usage)).into_response(),
+        Err(e) => {
+            error!("API: Storage measurement failed: {}", e);
+            StatusCode::INTERNAL_SERVER_ERROR.into_response()
+        }
+    }
+}
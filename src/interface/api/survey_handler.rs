@@ -0,0 +1,34 @@
+//! Post-call survey API handlers
+
+use super::cdr_dto::ApiResponse;
+use super::user_handler::AppState;
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    Json,
+};
+use tracing::error;
+use uuid::Uuid;
+
+/// Survey answers of a queue: per-question distributions and per-agent
+/// averages
+pub async fn get_queue_survey_results(State(state): State<AppState>, Path(id): Path<Uuid>) -> Response {
+    let Some(surveys) = &state.surveys else {
+        error!("Survey service not available");
+        return (
+            StatusCode::SERVICE_UNAVAILABLE,
+            Json(ApiResponse::<()>::error("Surveys not enabled".to_string())),
+        )
+            .into_response();
+    };
+    if surveys.assignment(id).is_none() && surveys.responses(id).is_empty() {
+        return (
+            StatusCode::NOT_FOUND,
+            Json(ApiResponse::<()>::error(format!("Queue {} has no survey", id))),
+        )
+            .into_response();
+    }
+
+    Json(ApiResponse::success(surveys.results(id))).into_response()
+}
@@ -0,0 +1,24 @@
+//! Branch survivability API handler
+
+use super::cdr_dto::ApiResponse;
+use super::user_handler::AppState;
+use axum::{
+    extract::State,
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    Json,
+};
+
+/// Whether this node runs without its upstream, and what it did meanwhile
+pub async fn get_survivability_status(State(state): State<AppState>) -> Response {
+    match &state.survivability {
+        Some(survivability) => Json(ApiResponse::success(survivability.status())).into_response(),
+        None => (
+            StatusCode::SERVICE_UNAVAILABLE,
+            Json(ApiResponse::<()>::error(
+                "Survivability not enabled".to_string(),
+            )),
+        )
+            .into_response(),
+    }
+}
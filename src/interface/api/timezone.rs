@@ -0,0 +1,124 @@
+//! Time zone of API requests
+//!
+//! Timestamps are stored in UTC and answered in RFC 3339 with an explicit
+//! offset. Endpoints that filter by date or report per day extract
+//! [`TimeZoneContext`], which reads the `tz` query parameter (an IANA name
+//! such as `Europe/Berlin`):
+//!
+//! - date-only filters (`from=2024-03-01`) start at midnight in that zone,
+//!   and a date-only upper bound includes the whole day
+//! - daily buckets follow the zone's calendar days, which are 23 or 25
+//!   hours long when daylight saving time starts or ends
+//! - timestamps in the response carry the zone's offset
+//!
+//! Without `tz`, user-scoped endpoints use the user's time zone, else their
+//! tenant's (`time_zones.tenants`), and other endpoints use
+//! `time_zones.default_zone`. Responses name the zone used in the
+//! `X-Time-Zone` header.
+
+use super::cdr_dto::ApiResponse;
+use super::user_handler::AppState;
+use crate::domain::shared::time_zone::{parse_time_bound, parse_time_zone};
+use crate::domain::shared::TimeZoneConfig;
+use crate::domain::user::User;
+use axum::{
+    async_trait,
+    extract::{FromRef, FromRequestParts, Query},
+    http::{request::Parts, StatusCode},
+    response::{IntoResponse, Response},
+    Json,
+};
+use chrono::{DateTime, Utc};
+use chrono_tz::Tz;
+use std::collections::HashMap;
+
+/// Response header naming the time zone of the response's timestamps
+pub const TIME_ZONE_HEADER: &str = "x-time-zone";
+
+/// Invalid time zone or time filter, answered with 400
+#[derive(Debug, Clone, PartialEq)]
+pub struct TimeZoneError(pub String);
+
+impl IntoResponse for TimeZoneError {
+    fn into_response(self) -> Response {
+        (
+            StatusCode::BAD_REQUEST,
+            Json(ApiResponse::<()>::error(self.0)),
+        )
+            .into_response()
+    }
+}
+
+impl FromRef<AppState> for TimeZoneConfig {
+    fn from_ref(state: &AppState) -> Self {
+        state.time_zones.clone()
+    }
+}
+
+/// Time zone asked for with `tz`, and the configured defaults
+#[derive(Debug, Clone)]
+pub struct TimeZoneContext {
+    requested: Option<Tz>,
+    config: TimeZoneConfig,
+}
+
+impl TimeZoneContext {
+    pub fn new(requested: Option<Tz>, config: TimeZoneConfig) -> Self {
+        Self { requested, config }
+    }
+
+    /// The zone asked for, else the default zone
+    pub fn zone(&self) -> Tz {
+        self.requested.unwrap_or_else(|| self.config.default_tz())
+    }
+
+    /// The zone asked for, else the user's, else their tenant's
+    pub fn zone_for_user(&self, user: &User) -> Tz {
+        self.requested.unwrap_or_else(|| {
+            self.config
+                .zone_for(Some(&user.realm), user.timezone.as_deref())
+        })
+    }
+
+    /// Parse the time filter `name`: RFC 3339, or a date in `zone`
+    ///
+    /// With `end_of_day` a date includes the whole day.
+    pub fn bound(
+        &self,
+        zone: Tz,
+        name: &str,
+        value: Option<&str>,
+        end_of_day: bool,
+    ) -> Result<Option<DateTime<Utc>>, TimeZoneError> {
+        value
+            .map(|value| {
+                parse_time_bound(value, zone, end_of_day)
+                    .map_err(|e| TimeZoneError(format!("{}: {}", name, e)))
+            })
+            .transpose()
+    }
+}
+
+/// `X-Time-Zone` header naming `zone`
+pub fn zone_header(zone: Tz) -> [(&'static str, &'static str); 1] {
+    [(TIME_ZONE_HEADER, zone.name())]
+}
+
+#[async_trait]
+impl<S> FromRequestParts<S> for TimeZoneContext
+where
+    TimeZoneConfig: FromRef<S>,
+    S: Send + Sync,
+{
+    type Rejection = TimeZoneError;
+
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        let Query(params) = Query::<HashMap<String, String>>::try_from_uri(&parts.uri)
+            .map_err(|e| TimeZoneError(format!("Invalid query string: {}", e)))?;
+        let requested = params
+            .get("tz")
+            .map(|tz| parse_time_zone(tz).map_err(TimeZoneError))
+            .transpose()?;
+        Ok(Self::new(requested, TimeZoneConfig::from_ref(state)))
+    }
+}
@@ -0,0 +1,109 @@
+//! Live caption API handlers
+//!
+//! `POST /api/calls/:call_id/transcription/start` captions a bridged call
+//! until it ends or `.../stop` is posted. Captions go out on the events
+//! WebSocket as `Caption` events.
+
+use super::cdr_dto::ApiResponse;
+use super::user_handler::AppState;
+use crate::infrastructure::transcription::{
+    CaptionLegs, LiveTranscriptionError, LiveTranscriptionService,
+};
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    Json,
+};
+use serde::Deserialize;
+use std::sync::Arc;
+use tracing::{error, info, warn};
+
+/// Body of a start request
+#[derive(Debug, Default, Deserialize)]
+pub struct StartTranscriptionRequest {
+    /// Language hint for the provider (BCP 47 or ISO 639-1)
+    #[serde(default)]
+    pub language: Option<String>,
+    /// `caller`, `callee` or `both` (the default)
+    #[serde(default)]
+    pub legs: CaptionLegs,
+}
+
+#[allow(clippy::result_large_err)]
+fn service(state: &AppState) -> Result<&Arc<LiveTranscriptionService>, Response> {
+    state.live_transcription.as_ref().ok_or_else(|| {
+        error!("Live transcription service not available");
+        (
+            StatusCode::SERVICE_UNAVAILABLE,
+            Json(ApiResponse::<()>::error("Live transcription not enabled".to_string())),
+        )
+            .into_response()
+    })
+}
+
+fn refused(e: LiveTranscriptionError) -> Response {
+    let status = match e {
+        LiveTranscriptionError::CallNotFound(_) | LiveTranscriptionError::NotRunning(_) => {
+            StatusCode::NOT_FOUND
+        }
+        LiveTranscriptionError::NoMedia(_) | LiveTranscriptionError::AlreadyRunning(_) => {
+            StatusCode::CONFLICT
+        }
+        LiveTranscriptionError::Provider(_) => StatusCode::BAD_GATEWAY,
+    };
+    warn!("API: Live transcription request refused: {}", e);
+    (status, Json(ApiResponse::<()>::error(e.to_string()))).into_response()
+}
+
+/// Start captioning a call
+pub async fn start_transcription(
+    State(state): State<AppState>,
+    Path(call_id): Path<String>,
+    Json(request): Json<StartTranscriptionRequest>,
+) -> Response {
+    let service = match service(&state) {
+        Ok(service) => service,
+        Err(response) => return response,
+    };
+    match service.start(&call_id, request.language, request.legs).await {
+        Ok(session) => {
+            info!("API: Captioning call {}", call_id);
+            (StatusCode::CREATED, Json(ApiResponse::success(session))).into_response()
+        }
+        Err(e) => refused(e),
+    }
+}
+
+/// The caption session of a call
+pub async fn get_transcription(
+    State(state): State<AppState>,
+    Path(call_id): Path<String>,
+) -> Response {
+    let service = match service(&state) {
+        Ok(service) => service,
+        Err(response) => return response,
+    };
+    match service.session(&call_id) {
+        Some(session) => Json(ApiResponse::success(session)).into_response(),
+        None => refused(LiveTranscriptionError::NotRunning(call_id)),
+    }
+}
+
+/// Stop captioning a call
+pub async fn stop_transcription(
+    State(state): State<AppState>,
+    Path(call_id): Path<String>,
+) -> Response {
+    let service = match service(&state) {
+        Ok(service) => service,
+        Err(response) => return response,
+    };
+    match service.stop(&call_id) {
+        Ok(session) => {
+            info!("API: Stopped captioning call {}", call_id);
+            Json(ApiResponse::success(session)).into_response()
+        }
+        Err(e) => refused(e),
+    }
+}
@@ -0,0 +1,130 @@
+//! Warm transfer API handlers
+//!
+//! `POST /api/calls/:call_id/transfer/consult` puts a call on hold and
+//! calls the target for the transferor to consult; once the target has
+//! answered, `.../transfer/complete`, `.../merge` or `.../cancel` settles
+//! it. Changes go out on the events WebSocket as `WarmTransfer` events.
+//! A consult retried with its `Idempotency-Key` after a failure does not
+//! call the target again.
+
+use super::cdr_dto::ApiResponse;
+use super::user_handler::AppState;
+use crate::infrastructure::idempotency::Idempotency;
+use crate::infrastructure::protocols::sip::{
+    TransferAction, WarmTransferError, WarmTransferManager,
+};
+use axum::{
+    extract::{Extension, Path, State},
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    Json,
+};
+use serde::Deserialize;
+use std::sync::Arc;
+use tracing::{error, info, warn};
+
+/// Body of a consult request
+#[derive(Debug, Deserialize)]
+pub struct ConsultRequest {
+    /// Number or SIP URI to consult
+    pub target: String,
+    /// URI of the party transferring, by default the callee
+    #[serde(default)]
+    pub transferor: Option<String>,
+}
+
+#[allow(clippy::result_large_err)]
+fn manager(state: &AppState) -> Result<&Arc<WarmTransferManager>, Response> {
+    state.warm_transfer.as_ref().ok_or_else(|| {
+        error!("Warm transfer not available");
+        (
+            StatusCode::SERVICE_UNAVAILABLE,
+            Json(ApiResponse::<()>::error("Warm transfer not enabled".to_string())),
+        )
+            .into_response()
+    })
+}
+
+fn refused(e: WarmTransferError) -> Response {
+    let status = match e {
+        WarmTransferError::CallNotFound(_) | WarmTransferError::NotInProgress(_) => {
+            StatusCode::NOT_FOUND
+        }
+        WarmTransferError::InProgress(_)
+        | WarmTransferError::NotAnswered(_)
+        | WarmTransferError::Call(_) => StatusCode::CONFLICT,
+        WarmTransferError::InvalidTarget(_) => StatusCode::BAD_REQUEST,
+        WarmTransferError::NoForwarder | WarmTransferError::NoConference(_) => {
+            StatusCode::SERVICE_UNAVAILABLE
+        }
+    };
+    warn!("API: Warm transfer request refused: {}", e);
+    (status, Json(ApiResponse::<()>::error(e.to_string()))).into_response()
+}
+
+/// The transfer in progress of a call
+pub async fn get_transfer(State(state): State<AppState>, Path(call_id): Path<String>) -> Response {
+    let manager = match manager(&state) {
+        Ok(manager) => manager,
+        Err(response) => return response,
+    };
+    match manager.session(&call_id) {
+        Some(session) => Json(ApiResponse::success(session)).into_response(),
+        None => refused(WarmTransferError::NotInProgress(call_id)),
+    }
+}
+
+/// Hold a call and call the target for the transferor to consult
+pub async fn consult_transfer(
+    State(state): State<AppState>,
+    Path(call_id): Path<String>,
+    idempotency: Option<Extension<Idempotency>>,
+    Json(request): Json<ConsultRequest>,
+) -> Response {
+    let manager = match manager(&state) {
+        Ok(manager) => manager,
+        Err(response) => return response,
+    };
+    if let Some(Extension(idempotency)) = idempotency {
+        if !idempotency.first("consult") {
+            if let Some(session) = manager.session(&call_id) {
+                return (StatusCode::CREATED, Json(ApiResponse::success(session))).into_response();
+            }
+        }
+    }
+    match manager
+        .consult(&call_id, &request.target, request.transferor.as_deref())
+        .await
+    {
+        Ok(session) => {
+            info!("API: Consulting {} about call {}", session.target, call_id);
+            (StatusCode::CREATED, Json(ApiResponse::success(session))).into_response()
+        }
+        Err(e) => refused(e),
+    }
+}
+
+/// Complete, merge or cancel the transfer of a call
+pub async fn settle_transfer(
+    State(state): State<AppState>,
+    Path((call_id, action)): Path<(String, String)>,
+) -> Response {
+    let manager = match manager(&state) {
+        Ok(manager) => manager,
+        Err(response) => return response,
+    };
+    let Some(action) = TransferAction::parse(&action) else {
+        return (
+            StatusCode::NOT_FOUND,
+            Json(ApiResponse::<()>::error(format!("Unknown transfer action {}", action))),
+        )
+            .into_response();
+    };
+    match manager.apply(&call_id, action).await {
+        Ok(session) => {
+            info!("API: Transfer of call {} {}", call_id, session.phase.as_str());
+            Json(ApiResponse::success(session)).into_response()
+        }
+        Err(e) => refused(e),
+    }
+}
@@ -0,0 +1,98 @@
+//! Trunk registration status and channel usage API handlers
+
+use super::cdr_dto::ApiResponse;
+use super::user_handler::AppState;
+use crate::infrastructure::protocols::sip::OutboundRegistration;
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    Json,
+};
+use std::sync::Arc;
+use tracing::{error, info};
+use uuid::Uuid;
+
+#[allow(clippy::result_large_err)]
+fn registration(state: &AppState) -> Result<&Arc<OutboundRegistration>, Response> {
+    state.outbound_registration.as_ref().ok_or_else(|| {
+        error!("Outbound registration not available");
+        (
+            StatusCode::SERVICE_UNAVAILABLE,
+            Json(ApiResponse::<()>::error(
+                "Outbound registration not enabled".to_string(),
+            )),
+        )
+            .into_response()
+    })
+}
+
+fn trunk_not_found(id: Uuid) -> Response {
+    (
+        StatusCode::NOT_FOUND,
+        Json(ApiResponse::<()>::error(format!(
+            "Trunk {} does not register",
+            id
+        ))),
+    )
+        .into_response()
+}
+
+/// Registration state of every trunk that registers upstream
+pub async fn list_trunk_registrations(State(state): State<AppState>) -> Response {
+    let registration = match registration(&state) {
+        Ok(registration) => registration,
+        Err(response) => return response,
+    };
+
+    Json(ApiResponse::success(registration.statuses())).into_response()
+}
+
+/// Registration state of one trunk
+pub async fn get_trunk_registration(
+    State(state): State<AppState>,
+    Path(id): Path<Uuid>,
+) -> Response {
+    let registration = match registration(&state) {
+        Ok(registration) => registration,
+        Err(response) => return response,
+    };
+
+    match registration.status(id) {
+        Some(status) => Json(ApiResponse::success(status)).into_response(),
+        None => trunk_not_found(id),
+    }
+}
+
+/// Register a trunk again right away, e.g. after fixing refused credentials
+pub async fn reregister_trunk(State(state): State<AppState>, Path(id): Path<Uuid>) -> Response {
+    let registration = match registration(&state) {
+        Ok(registration) => registration,
+        Err(response) => return response,
+    };
+
+    if !registration.register_now(id) {
+        return trunk_not_found(id);
+    }
+    info!("API: Registration of trunk {} triggered", id);
+    (
+        StatusCode::ACCEPTED,
+        Json(ApiResponse::success(registration.status(id))),
+    )
+        .into_response()
+}
+
+/// Channels each trunk is using per direction, with its limits
+pub async fn list_trunk_channels(State(state): State<AppState>) -> Response {
+    let Some(trunks) = &state.trunk_channels else {
+        return (
+            StatusCode::SERVICE_UNAVAILABLE,
+            Json(ApiResponse::<()>::error(
+                "Trunk channel limits not configured".to_string(),
+            )),
+        )
+            .into_response();
+    };
+
+    Json(ApiResponse::success(trunks.occupancy())).into_response()
+}
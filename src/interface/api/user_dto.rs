@@ -1,7 +1,9 @@
 //! User API DTOs (Data Transfer Objects)
 
+use super::openapi::{boolean, date_time, integer, nullable, object, string, ApiSchema};
 use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
 
 /// User response DTO
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -11,20 +13,14 @@ pub struct UserResponse {
     pub realm: String,
     pub display_name: Option<String>,
     pub email: Option<String>,
+    pub department: Option<String>,
+    pub locale: Option<String>,
+    pub timezone: Option<String>,
     pub enabled: bool,
     pub created_at: DateTime<Utc>,
     pub updated_at: DateTime<Utc>,
 }
 
-/// User list response
-#[derive(Debug, Serialize)]
-pub struct UserListResponse {
-    pub users: Vec<UserResponse>,
-    pub total: i64,
-    pub limit: i64,
-    pub offset: i64,
-}
-
 /// Create user request
 #[derive(Debug, Deserialize)]
 pub struct CreateUserRequest {
@@ -33,6 +29,12 @@ pub struct CreateUserRequest {
     pub realm: String,
     pub display_name: Option<String>,
     pub email: Option<String>,
+    #[serde(default)]
+    pub department: Option<String>,
+    #[serde(default)]
+    pub locale: Option<String>,
+    #[serde(default)]
+    pub timezone: Option<String>,
 }
 
 /// Update user request
@@ -40,6 +42,12 @@ pub struct CreateUserRequest {
 pub struct UpdateUserRequest {
     pub display_name: Option<String>,
     pub email: Option<String>,
+    #[serde(default)]
+    pub department: Option<String>,
+    #[serde(default)]
+    pub locale: Option<String>,
+    #[serde(default)]
+    pub timezone: Option<String>,
     pub enabled: Option<bool>,
 }
 
@@ -83,6 +91,80 @@ pub struct DeleteResponse {
     pub deleted: bool,
 }
 
+impl ApiSchema for UserResponse {
+    const NAME: &'static str = "User";
+
+    fn schema() -> Value {
+        object(json!({
+            "id": integer(),
+            "username": string(),
+            "realm": string(),
+            "display_name": nullable(string()),
+            "email": nullable(string()),
+            "department": nullable(string()),
+            "locale": nullable(string()),
+            "timezone": nullable(string()),
+            "enabled": boolean(),
+            "created_at": date_time(),
+            "updated_at": date_time(),
+        }))
+    }
+}
+
+impl ApiSchema for CreateUserRequest {
+    const NAME: &'static str = "CreateUserRequest";
+
+    fn schema() -> Value {
+        object(json!({
+            "username": string(),
+            "password": string(),
+            "realm": string(),
+            "display_name": nullable(string()),
+            "email": nullable(string()),
+            "department": nullable(string()),
+            "locale": nullable(string()),
+            "timezone": nullable(string()),
+        }))
+    }
+}
+
+impl ApiSchema for UpdateUserRequest {
+    const NAME: &'static str = "UpdateUserRequest";
+
+    fn schema() -> Value {
+        object(json!({
+            "display_name": nullable(string()),
+            "email": nullable(string()),
+            "department": nullable(string()),
+            "locale": nullable(string()),
+            "timezone": nullable(string()),
+            "enabled": nullable(boolean()),
+        }))
+    }
+}
+
+impl ApiSchema for ChangePasswordRequest {
+    const NAME: &'static str = "ChangePasswordRequest";
+
+    fn schema() -> Value {
+        object(json!({
+            "old_password": string(),
+            "new_password": string(),
+        }))
+    }
+}
+
+impl ApiSchema for DeleteResponse {
+    const NAME: &'static str = "DeleteResponse";
+
+    fn schema() -> Value {
+        object(json!({
+            "id": integer(),
+            "deleted": boolean(),
+        }))
+    }
+}
+
 /// Convert domain User to UserResponse
 impl From<crate::domain::user::User> for UserResponse {
     fn from(user: crate::domain::user::User) -> Self {
@@ -92,6 +174,9 @@ impl From<crate::domain::user::User> for UserResponse {
             realm: user.realm,
             display_name: user.display_name,
             email: user.email,
+            department: user.department,
+            locale: user.locale,
+            timezone: user.timezone,
             enabled: user.enabled,
             created_at: user.created_at,
             updated_at: user.updated_at,
@@ -108,6 +193,10 @@ impl From<CreateUserRequest> for crate::domain::user::CreateUser {
             realm: req.realm,
             display_name: req.display_name,
             email: req.email,
+            role_id: None,
+            department: req.department,
+            locale: req.locale,
+            timezone: req.timezone,
         }
     }
 }
@@ -118,7 +207,11 @@ impl From<UpdateUserRequest> for crate::domain::user::UpdateUser {
         Self {
             display_name: req.display_name,
             email: req.email,
+            department: req.department,
+            locale: req.locale,
+            timezone: req.timezone,
             enabled: req.enabled,
+            role_id: None,
         }
     }
 }
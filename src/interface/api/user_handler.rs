@@ -1,21 +1,30 @@
 //! User API handlers
 
+use super::openapi::{
+    any, array, boolean, date_time, integer, nullable, object, one_of, reference, string,
+    ApiSchema,
+};
+use super::pagination::Pagination;
 use super::user_dto::{
     ApiResponse, ChangePasswordRequest, CreateUserRequest, DeleteResponse, UpdateUserRequest,
-    UserListResponse, UserResponse,
+    UserResponse,
 };
+use crate::domain::shared::time_zone::parse_time_zone;
+use crate::domain::shared::SortOrder;
 use crate::domain::user::UserRepository;
 use axum::{
     extract::{Path, Query, State},
     http::StatusCode,
+    response::{IntoResponse, Response},
     Json,
 };
 use serde::Deserialize;
+use serde_json::{json, Value};
 use std::sync::Arc;
 use tracing::{error, info};
 
 use crate::domain::cdr::CdrRepository;
-use crate::infrastructure::protocols::sip::{CallRouter, Registrar};
+use crate::infrastructure::protocols::sip::{CallRouter, Registrar, RegistrationEvent};
 use super::ws_handler::EventBroadcaster;
 
 /// Application state
@@ -28,21 +37,69 @@ pub struct AppState {
     pub event_broadcaster: Option<Arc<EventBroadcaster>>,
     pub conference_repository: Option<Arc<dyn crate::domain::conference::ConferenceRepository>>,
     pub conference_manager: Option<Arc<crate::domain::conference_manager::ConferenceManager>>,
+    pub missed_call_tracker: Option<Arc<crate::domain::call_history::MissedCallTracker>>,
+    pub db_health: Option<Arc<crate::infrastructure::persistence::health::DbHealth>>,
+    pub audio_library: Option<Arc<crate::domain::audio::AudioLibrary>>,
+    pub speed_dial_repository: Option<Arc<dyn crate::domain::speed_dial::SpeedDialRepository>>,
+    pub fraud_detector: Option<Arc<crate::domain::fraud_detection::FraudDetector>>,
+    pub class_of_service: Option<Arc<crate::domain::class_of_service::ClassOfServiceRegistry>>,
+    pub call_queue_repository: Option<Arc<dyn crate::domain::call_queue::CallQueueRepository>>,
+    pub queue_event_repository: Option<Arc<dyn crate::domain::queue_reporting::QueueEventRepository>>,
+    pub diagnostics: Option<Arc<super::diagnostics_handler::DiagnosticsContext>>,
+    pub device_tokens: Option<Arc<crate::domain::device_token::DeviceTokenStore>>,
+    pub queue_engine: Option<Arc<crate::domain::call_queue_engine::CallQueueEngine>>,
+    pub replication: Option<Arc<crate::infrastructure::replication::ReplicationNode>>,
+    pub message_repository: Option<Arc<dyn crate::domain::instant_messaging::MessageRepository>>,
+    pub voicemail_repository: Option<Arc<dyn crate::domain::voicemail::VoicemailRepository>>,
+    pub voicemail_lists: Option<Arc<crate::domain::voicemail_list::VoicemailListService>>,
+    pub outbound_registration: Option<Arc<crate::infrastructure::protocols::sip::OutboundRegistration>>,
+    pub branding: Option<Arc<crate::domain::tenant_branding::BrandingRegistry>>,
+    pub moh_classes: Option<Arc<crate::infrastructure::media::MohClassRegistry>>,
+    pub surveys: Option<Arc<crate::domain::call_survey::SurveyService>>,
+    pub cdr_retention: Option<Arc<crate::application::call::CdrRetentionService>>,
+    pub header_rules: Option<Arc<crate::infrastructure::protocols::sip::HeaderRulesEngine>>,
+    pub call_debug: Option<Arc<crate::infrastructure::call_debug::CallDebugRegistry>>,
+    pub numbering: Option<Arc<crate::domain::shared::NumberingPlan>>,
+    pub config_report: Option<Arc<crate::config::PreflightReport>>,
+    pub chat: Option<Arc<crate::application::chat::ChatService>>,
+    pub feature_codes: Option<Arc<crate::domain::feature_code::FeatureCodeRegistry>>,
+    pub device_resync: Option<Arc<crate::infrastructure::protocols::sip::DeviceResyncer>>,
+    pub capacity: Option<Arc<crate::infrastructure::media::CapacityMonitor>>,
+    pub overload: Option<Arc<crate::infrastructure::protocols::sip::OverloadMonitor>>,
+    pub maintenance: Option<Arc<crate::infrastructure::protocols::sip::MaintenanceRegistry>>,
+    pub call_spans: Option<Arc<crate::infrastructure::telemetry::CallSpans>>,
+    pub announcements: Option<Arc<crate::domain::routing::AnnouncementService>>,
+    pub auto_attendants: Option<Arc<crate::domain::routing::AutoAttendantService>>,
+    pub storage: Option<Arc<crate::infrastructure::storage::StorageGuard>>,
+    pub setup_latency: Option<Arc<crate::infrastructure::setup_latency::SetupLatencyMonitor>>,
+    pub enrollment: Option<Arc<crate::domain::device_enrollment::EnrollmentService>>,
+    pub lnp: Option<Arc<crate::infrastructure::lnp::LnpResolver>>,
+    pub routing_simulator: Option<Arc<crate::domain::routing::RoutingSimulator>>,
+    pub routing_linter: Option<Arc<crate::domain::routing::RoutingLinter>>,
+    pub campaigns: Option<Arc<crate::application::campaign::CampaignService>>,
+    pub scheduled_calls: Option<Arc<crate::application::scheduled_call::ScheduledCallService>>,
+    pub live_transcription: Option<Arc<crate::infrastructure::transcription::LiveTranscriptionService>>,
+    pub warm_transfer: Option<Arc<crate::infrastructure::protocols::sip::WarmTransferManager>>,
+    pub privacy: Option<Arc<crate::application::privacy::AnonymizationService>>,
+    pub idempotency: Option<Arc<crate::infrastructure::idempotency::IdempotencyCache>>,
+    pub survivability: Option<Arc<crate::infrastructure::protocols::sip::SurvivabilityManager>>,
+    pub trunk_channels: Option<Arc<crate::infrastructure::protocols::sip::TrunkManager>>,
+    pub pagination: crate::config::PaginationConfig,
+    pub call_control: crate::config::CallControlConfig,
+    /// Whether `/api/admin/docs` serves Swagger UI
+    pub swagger_ui: bool,
+    pub answer_supervision: crate::domain::billing::AnswerSupervisionConfig,
+    pub time_zones: crate::domain::shared::TimeZoneConfig,
 }
 
-/// Query parameters for listing users
+/// Query parameters for listing users, besides [`Pagination`]
 #[derive(Debug, Deserialize)]
 pub struct ListUsersQuery {
-    #[serde(default = "default_limit")]
-    pub limit: i64,
-    #[serde(default)]
-    pub offset: i64,
     pub realm: Option<String>,
 }
 
-fn default_limit() -> i64 {
-    50
-}
+/// Fields users can be sorted by
+const USER_SORT_FIELDS: &[&str] = &["id", "username", "display_name", "realm", "created_at"];
 
 /// Create a new user
 pub async fn create_user(
@@ -51,6 +108,10 @@ pub async fn create_user(
 ) -> Result<(StatusCode, Json<ApiResponse<UserResponse>>), StatusCode> {
     info!("API: Creating user {}", req.username);
 
+    if let Some(Err(e)) = req.timezone.as_deref().map(parse_time_zone) {
+        return Ok((StatusCode::BAD_REQUEST, Json(ApiResponse::error(e))));
+    }
+
     let create_data = req.into();
 
     match state.user_repository.create(create_data).await {
@@ -111,46 +172,47 @@ pub async fn get_user_by_username(
 /// List users
 pub async fn list_users(
     State(state): State<AppState>,
+    page: Pagination,
     Query(query): Query<ListUsersQuery>,
-) -> Result<Json<ApiResponse<UserListResponse>>, StatusCode> {
+) -> Response {
     info!(
         "API: Listing users (limit: {}, offset: {}, realm: {:?})",
-        query.limit, query.offset, query.realm
+        page.limit, page.offset, query.realm
     );
 
+    let sort = match page.sort(USER_SORT_FIELDS, SortOrder::ascending("created_at")) {
+        Ok(sort) => sort,
+        Err(e) => return e.into_response(),
+    };
+
     // Get users
     let users_result = if let Some(realm) = &query.realm {
         state
             .user_repository
-            .list_by_realm(realm, query.limit, query.offset)
+            .list_by_realm(realm, &sort, page.fetch_limit(), page.offset)
             .await
     } else {
         state
             .user_repository
-            .list(query.limit, query.offset)
+            .list(&sort, page.fetch_limit(), page.offset)
             .await
     };
 
-    // Get total count
-    let count_result = if let Some(realm) = &query.realm {
-        state.user_repository.count_by_realm(realm).await
-    } else {
-        state.user_repository.count().await
+    // Get total count, only when asked for
+    let count_result = match (&query.realm, page.count) {
+        (_, false) => Ok(None),
+        (Some(realm), true) => state.user_repository.count_by_realm(realm).await.map(Some),
+        (None, true) => state.user_repository.count().await.map(Some),
     };
 
     match (users_result, count_result) {
         (Ok(users), Ok(total)) => {
-            let response = UserListResponse {
-                users: users.into_iter().map(|u| u.into()).collect(),
-                total,
-                limit: query.limit,
-                offset: query.offset,
-            };
-            Ok(Json(ApiResponse::success(response)))
+            let users: Vec<UserResponse> = users.into_iter().map(|u| u.into()).collect();
+            page.respond(users, total)
         }
         (Err(e), _) | (_, Err(e)) => {
             error!("API: Failed to list users: {}", e);
-            Ok(Json(ApiResponse::error(e.to_string())))
+            Json(ApiResponse::<()>::error(e.to_string())).into_response()
         }
     }
 }
@@ -163,11 +225,22 @@ pub async fn update_user(
 ) -> Result<Json<ApiResponse<UserResponse>>, StatusCode> {
     info!("API: Updating user ID: {}", id);
 
+    if let Some(Err(e)) = req.timezone.as_deref().map(parse_time_zone) {
+        return Ok(Json(ApiResponse::error(e)));
+    }
+
+    // Phones show the display name
+    let resync = req.display_name.is_some();
     let update_data = req.into();
 
     match state.user_repository.update(id, update_data).await {
         Ok(user) => {
             info!("API: Updated user {} (ID: {})", user.username, user.id);
+            if resync {
+                if let Some(resyncer) = &state.device_resync {
+                    resyncer.schedule_user(&user.username);
+                }
+            }
             Ok(Json(ApiResponse::success(user.into())))
         }
         Err(e) => {
@@ -331,6 +404,40 @@ pub async fn get_user_registration_status(
     Ok(Json(ApiResponse::success(status)))
 }
 
+/// Get the recent registration events of a user (last 20 per AoR)
+pub async fn get_registration_history(
+    State(state): State<AppState>,
+    Path(id): Path<i32>,
+) -> Result<Json<ApiResponse<RegistrationHistoryResponse>>, StatusCode> {
+    info!("API: Getting registration history for user ID: {}", id);
+
+    let registrar = match &state.registrar {
+        Some(reg) => reg,
+        None => {
+            error!("Registrar not available");
+            return Ok(Json(ApiResponse::error(
+                "Registrar not available".to_string(),
+            )));
+        }
+    };
+
+    let user = match state.user_repository.find_by_id(id).await {
+        Ok(Some(user)) => user,
+        Ok(None) => return Err(StatusCode::NOT_FOUND),
+        Err(e) => {
+            error!("API: Failed to get user: {}", e);
+            return Err(StatusCode::INTERNAL_SERVER_ERROR);
+        }
+    };
+
+    let events = registrar.registration_history_for_user(&user.username);
+
+    Ok(Json(ApiResponse::success(RegistrationHistoryResponse {
+        username: user.username,
+        events,
+    })))
+}
+
 /// Get online user count
 pub async fn get_online_count(
     State(state): State<AppState>,
@@ -375,8 +482,239 @@ pub struct UserRegistrationStatus {
     pub bindings: Vec<BindingInfo>,
 }
 
+/// Registration history response
+#[derive(Debug, serde::Serialize, serde::Deserialize)]
+pub struct RegistrationHistoryResponse {
+    pub username: String,
+    pub events: Vec<RegistrationEvent>,
+}
+
 /// Online count response
 #[derive(Debug, serde::Serialize, serde::Deserialize)]
 pub struct OnlineCountResponse {
     pub count: usize,
 }
+
+impl ApiSchema for RegistrationInfo {
+    const NAME: &'static str = "Registration";
+
+    fn schema() -> Value {
+        object(json!({
+            "aor": string(),
+            "bindings": array(reference::<BindingInfo>()),
+        }))
+    }
+}
+
+impl ApiSchema for BindingInfo {
+    const NAME: &'static str = "Binding";
+
+    fn schema() -> Value {
+        object(json!({
+            "contact": string(),
+            "expires_at": string(),
+            "user_agent": nullable(string()),
+        }))
+    }
+}
+
+impl ApiSchema for UserRegistrationStatus {
+    const NAME: &'static str = "RegistrationStatus";
+
+    fn schema() -> Value {
+        object(json!({
+            "username": string(),
+            "is_online": boolean(),
+            "bindings": array(reference::<BindingInfo>()),
+        }))
+    }
+}
+
+impl ApiSchema for RegistrationHistoryResponse {
+    const NAME: &'static str = "RegistrationHistory";
+
+    fn schema() -> Value {
+        object(json!({
+            "username": string(),
+            "events": array(reference::<RegistrationEvent>()),
+        }))
+    }
+}
+
+impl ApiSchema for RegistrationEvent {
+    const NAME: &'static str = "RegistrationEvent";
+
+    fn schema() -> Value {
+        object(json!({
+            "type": one_of(&["added", "refreshed", "expired", "removed", "churn"]),
+            "aor": string(),
+            "contact": nullable(string()),
+            "source_ip": nullable(string()),
+            "user_agent": nullable(string()),
+            "expires": integer(),
+            "replaced_existing": boolean(),
+            "timestamp": date_time(),
+            "churn": nullable(any()),
+        }))
+    }
+}
+
+impl ApiSchema for OnlineCountResponse {
+    const NAME: &'static str = "OnlineCount";
+
+    fn schema() -> Value {
+        object(json!({ "count": integer() }))
+    }
+}
+
+#[cfg(test)]
+impl AppState {
+    /// State with only a user repository, for handler tests
+    pub(crate) fn for_tests(user_repository: Arc<dyn UserRepository>) -> Self {
+        Self {
+            user_repository,
+            cdr_repository: None,
+            call_router: None,
+            registrar: None,
+            event_broadcaster: None,
+            conference_repository: None,
+            conference_manager: None,
+            missed_call_tracker: None,
+            db_health: None,
+            audio_library: None,
+            speed_dial_repository: None,
+            fraud_detector: None,
+            class_of_service: None,
+            call_queue_repository: None,
+            queue_event_repository: None,
+            diagnostics: None,
+            device_tokens: None,
+            queue_engine: None,
+            replication: None,
+            message_repository: None,
+            voicemail_repository: None,
+            voicemail_lists: None,
+            outbound_registration: None,
+            branding: None,
+            moh_classes: None,
+            surveys: None,
+            cdr_retention: None,
+            header_rules: None,
+            call_debug: None,
+            numbering: None,
+            config_report: None,
+            chat: None,
+            feature_codes: None,
+            device_resync: None,
+            capacity: None,
+            maintenance: None,
+            call_spans: None,
+            announcements: None,
+            auto_attendants: None,
+            overload: None,
+            storage: None,
+            setup_latency: None,
+            enrollment: None,
+            lnp: None,
+            routing_simulator: None,
+            routing_linter: None,
+            campaigns: None,
+            scheduled_calls: None,
+            live_transcription: None,
+            warm_transfer: None,
+            privacy: None,
+            idempotency: None,
+            survivability: None,
+            trunk_channels: None,
+            pagination: Default::default(),
+            call_control: Default::default(),
+            swagger_ui: false,
+            answer_supervision: Default::default(),
+            time_zones: Default::default(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::domain::user::repository::MockUserRepository;
+    use crate::domain::user::User;
+    use axum::{body::Body, http::Request, routing::get, Router};
+    use serde_json::Value;
+    use tower::ServiceExt;
+
+    fn user(id: i32, username: &str) -> User {
+        User {
+            id,
+            username: username.to_string(),
+            password_hash: String::new(),
+            sip_ha1: None,
+            realm: "example.com".to_string(),
+            display_name: None,
+            email: None,
+            department: None,
+            locale: None,
+            timezone: None,
+            enabled: true,
+            role_id: None,
+            created_at: chrono::Utc::now(),
+            updated_at: chrono::Utc::now(),
+        }
+    }
+
+    async fn get_json(app: &Router, uri: &str) -> Value {
+        let response = app
+            .clone()
+            .oneshot(Request::builder().uri(uri).body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        serde_json::from_slice(&body).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_list_users_pages_in_stable_order() {
+        let mut users = MockUserRepository::new();
+        users.expect_list().returning(|sort, limit, offset| {
+            assert_eq!(sort, &SortOrder::descending("username"));
+            // The database's answer: ordered by username, then id
+            let mut all: Vec<User> = ["carol", "alice", "dave", "bob", "erin"]
+                .iter()
+                .enumerate()
+                .map(|(i, name)| user(i as i32 + 1, name))
+                .collect();
+            all.sort_by(|a, b| b.username.cmp(&a.username).then(b.id.cmp(&a.id)));
+            Ok(all
+                .into_iter()
+                .skip(offset as usize)
+                .take(limit as usize)
+                .collect())
+        });
+        users.expect_count().returning(|| Ok(5));
+        let app = Router::new()
+            .route("/users", get(list_users))
+            .with_state(AppState::for_tests(Arc::new(users)));
+
+        let mut listed = Vec::new();
+        let mut uri = "/users?limit=2&sort=-username&count=true".to_string();
+        for _ in 0..5 {
+            let json = get_json(&app, &uri).await;
+            assert_eq!(json["data"]["total"], 5);
+            for user in json["data"]["items"].as_array().unwrap() {
+                listed.push(user["username"].as_str().unwrap().to_string());
+            }
+            match json["data"]["next_cursor"].as_str() {
+                Some(cursor) => {
+                    uri = format!("/users?limit=2&sort=-username&count=true&cursor={}", cursor)
+                }
+                None => break,
+            }
+        }
+
+        assert_eq!(listed, ["erin", "dave", "carol", "bob", "alice"]);
+    }
+}
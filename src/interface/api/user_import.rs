@@ -36,6 +36,12 @@ struct CsvUserRecord {
     realm: String,
     display_name: Option<String>,
     email: Option<String>,
+    #[serde(default)]
+    department: Option<String>,
+    #[serde(default)]
+    locale: Option<String>,
+    #[serde(default)]
+    timezone: Option<String>,
 }
 
 /// Handle bulk user import from CSV
@@ -101,6 +107,9 @@ pub async fn import_users_csv(
                     realm: record.realm,
                     display_name: record.display_name,
                     email: record.email,
+                    department: record.department,
+                    locale: record.locale,
+                    timezone: record.timezone,
                     role_id: None, // Default role
                 };
 
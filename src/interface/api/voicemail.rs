@@ -43,6 +43,7 @@ struct MailboxRequest {
     user_id: Option<i32>,
     pin: Option<String>,
     greeting_file: Option<String>,
+    name_greeting_file: Option<String>,
     max_message_duration: Option<u32>,
     max_messages: Option<u32>,
     email_notification: Option<bool>,
@@ -56,6 +57,7 @@ struct MailboxResponse {
     user_id: i32,
     pin: Option<String>,
     greeting_file: Option<String>,
+    name_greeting_file: Option<String>,
     max_message_duration: u32,
     max_messages: u32,
     email_notification: bool,
@@ -71,6 +73,7 @@ impl From<VoicemailMailbox> for MailboxResponse {
             user_id: mailbox.user_id,
             pin: mailbox.pin,
             greeting_file: mailbox.greeting_file,
+            name_greeting_file: mailbox.name_greeting_file,
             max_message_duration: mailbox.max_message_duration,
             max_messages: mailbox.max_messages,
             email_notification: mailbox.email_notification,
@@ -198,6 +201,9 @@ async fn update_mailbox(
     if let Some(greeting) = req.greeting_file {
         mailbox.greeting_file = Some(greeting);
     }
+    if let Some(name_greeting) = req.name_greeting_file {
+        mailbox.name_greeting_file = Some(name_greeting);
+    }
     if let Some(max_duration) = req.max_message_duration {
         mailbox.max_message_duration = max_duration;
     }
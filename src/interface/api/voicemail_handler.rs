@@ -0,0 +1,876 @@
+//! Voicemail API handlers
+//!
+//! Visual voicemail for mobile and desktop clients: list a user's messages
+//! with transcripts, stream the recording (with `Range` support so players
+//! can seek), mark messages read or unread, delete them and forward them to
+//! another mailbox. Mailboxes are named after the owner's SIP username.
+//!
+//! Credentials are checked as for diagnostics: only the mailbox owner or a
+//! user holding `voicemail:manage` gets in, and every access is written to
+//! the security audit log.
+
+use super::cdr_dto::ApiResponse;
+use super::diagnostics_handler::{authenticate, client_ip, DiagnosticsContext};
+use super::user_handler::AppState;
+use crate::domain::security::{SecurityEvent, SecuritySeverity};
+use crate::domain::user::Permission;
+use crate::domain::voicemail::{VoicemailMessage, VoicemailRepository, VoicemailStatus};
+use crate::domain::voicemail_service::VoicemailService;
+use axum::{
+    body::Body,
+    extract::{Path, Query, State},
+    http::{header, HeaderMap, StatusCode},
+    response::{IntoResponse, Response},
+    Json,
+};
+use bytes::Bytes;
+use chrono::{DateTime, Utc};
+use futures::Stream;
+use serde::{Deserialize, Serialize};
+use std::io::{self, SeekFrom};
+use std::path::PathBuf;
+use std::sync::Arc;
+use tokio::io::{AsyncReadExt, AsyncSeekExt};
+use tracing::{error, info, warn};
+use uuid::Uuid;
+
+/// Bytes read from a recording per streamed chunk
+const AUDIO_CHUNK_BYTES: usize = 64 * 1024;
+
+/// Recordings never change once deposited; clients may keep them for a day
+const AUDIO_CACHE_CONTROL: &str = "private, max-age=86400";
+
+/// Query parameters for the voicemail listing
+#[derive(Debug, Deserialize)]
+pub struct VoicemailListQuery {
+    /// Only messages with this status (new, read, saved); deleted messages
+    /// are listed only when asked for
+    pub status: Option<String>,
+}
+
+/// A voicemail message
+#[derive(Debug, Serialize, Deserialize)]
+pub struct VoicemailDto {
+    pub id: Uuid,
+    pub caller: String,
+    pub caller_name: Option<String>,
+    pub duration_seconds: u32,
+    pub status: String,
+    pub created_at: DateTime<Utc>,
+    pub read_at: Option<DateTime<Utc>>,
+    /// Speech-to-text of the message, when transcribed
+    pub transcript: Option<String>,
+    pub transcript_confidence: Option<f32>,
+    /// Distribution list the message was sent to
+    pub via_list: Option<String>,
+    /// Caller as shown on phones, e.g. "Bob (via Sales Team)"
+    pub display_caller: String,
+}
+
+impl From<VoicemailMessage> for VoicemailDto {
+    fn from(message: VoicemailMessage) -> Self {
+        let display_caller = message.display_caller();
+        Self {
+            id: message.id,
+            caller: message.caller,
+            caller_name: message.caller_name,
+            duration_seconds: message.duration_seconds,
+            status: format!("{:?}", message.status).to_lowercase(),
+            created_at: message.created_at,
+            read_at: message.read_at,
+            transcript: message.transcript,
+            transcript_confidence: message.transcript_confidence,
+            via_list: message.via_list,
+            display_caller,
+        }
+    }
+}
+
+/// Request to mark a message read or unread
+#[derive(Debug, Deserialize)]
+pub struct UpdateVoicemailRequest {
+    pub read: bool,
+}
+
+/// Request to forward a message
+#[derive(Debug, Deserialize)]
+pub struct ForwardVoicemailRequest {
+    /// Mailbox (SIP username) receiving the copy
+    pub mailbox: String,
+}
+
+fn parse_status(status: &str) -> Option<VoicemailStatus> {
+    match status.to_lowercase().as_str() {
+        "new" => Some(VoicemailStatus::New),
+        "read" => Some(VoicemailStatus::Read),
+        "saved" => Some(VoicemailStatus::Saved),
+        "deleted" => Some(VoicemailStatus::Deleted),
+        _ => None,
+    }
+}
+
+fn unavailable(what: &str) -> Response {
+    error!("{} not available", what);
+    (
+        StatusCode::SERVICE_UNAVAILABLE,
+        Json(ApiResponse::<()>::error(format!("{} not available", what))),
+    )
+        .into_response()
+}
+
+fn not_found(message: String) -> Response {
+    (StatusCode::NOT_FOUND, Json(ApiResponse::<()>::error(message))).into_response()
+}
+
+/// A mailbox opened by an authorized user
+struct MailboxAccess {
+    repository: Arc<dyn VoicemailRepository>,
+    diagnostics: Arc<DiagnosticsContext>,
+    /// Mailbox ID (the owner's SIP username)
+    mailbox: String,
+    /// User who opened it
+    username: String,
+    ip: String,
+}
+
+impl MailboxAccess {
+    /// Record an access to the mailbox or one of its messages
+    fn audit(&self, action: &str, message: Option<Uuid>) {
+        let resource = match message {
+            Some(id) => format!("voicemail:{}/{}", self.mailbox, id),
+            None => format!("voicemail:{}", self.mailbox),
+        };
+        // Another user's mailbox is only opened by administrators
+        let severity = if self.username == self.mailbox {
+            SecuritySeverity::Info
+        } else {
+            SecuritySeverity::Low
+        };
+        self.diagnostics.audit(
+            SecurityEvent::DataAccess {
+                username: self.username.clone(),
+                ip: self.ip.clone(),
+                resource,
+                action: action.to_string(),
+            },
+            severity,
+        );
+    }
+
+    /// Message `id` of this mailbox; deleted messages are not found
+    async fn message(&self, id: Uuid) -> Result<VoicemailMessage, Response> {
+        match self.repository.get_message(id).await {
+            Ok(Some(message))
+                if message.mailbox_id == self.mailbox
+                    && message.status != VoicemailStatus::Deleted =>
+            {
+                Ok(message)
+            }
+            Ok(_) => Err(not_found(format!("Voicemail {} not found", id))),
+            Err(e) => {
+                error!("API: Failed to get voicemail {}: {}", id, e);
+                Err(StatusCode::INTERNAL_SERVER_ERROR.into_response())
+            }
+        }
+    }
+}
+
+/// Open the mailbox of user `id` for `action` after checking credentials:
+/// the owner, or a user with `voicemail:manage`
+async fn open_mailbox(
+    state: &AppState,
+    headers: &HeaderMap,
+    id: i32,
+    action: &str,
+) -> Result<MailboxAccess, Response> {
+    let Some(diagnostics) = state.diagnostics.clone() else {
+        return Err(unavailable("Authentication"));
+    };
+    let Some(repository) = state.voicemail_repository.clone() else {
+        return Err(unavailable("Voicemail repository"));
+    };
+
+    let ip = client_ip(headers);
+    let (user, permissions) = authenticate(state, &diagnostics, headers).await?;
+
+    let owner = match state.user_repository.find_by_id(id).await {
+        Ok(Some(owner)) => owner,
+        Ok(None) => return Err(not_found(format!("User {} not found", id))),
+        Err(e) => {
+            error!("API: Failed to get user: {}", e);
+            return Err(StatusCode::INTERNAL_SERVER_ERROR.into_response());
+        }
+    };
+
+    if user.id != owner.id && !permissions.contains(&Permission::VoicemailManage) {
+        warn!("API: {} denied access to the mailbox of {}", user.username, owner.username);
+        diagnostics.audit(
+            SecurityEvent::PermissionDenied {
+                username: user.username,
+                ip,
+                resource: format!("voicemail:{}", owner.username),
+                action: action.to_string(),
+            },
+            SecuritySeverity::Medium,
+        );
+        return Err((
+            StatusCode::FORBIDDEN,
+            Json(ApiResponse::<()>::error(format!(
+                "Only the mailbox owner or {} may access this mailbox",
+                Permission::VoicemailManage.as_str()
+            ))),
+        )
+            .into_response());
+    }
+
+    Ok(MailboxAccess {
+        repository,
+        diagnostics,
+        mailbox: owner.username,
+        username: user.username,
+        ip,
+    })
+}
+
+/// Voicemail base directory, with a directory per mailbox
+fn voicemail_dir(state: &AppState) -> Option<PathBuf> {
+    state.storage.as_ref()?.config().voicemail.dir.clone()
+}
+
+/// Get a user's voicemail, newest first
+pub async fn list_user_voicemail(
+    State(state): State<AppState>,
+    Path(id): Path<i32>,
+    Query(query): Query<VoicemailListQuery>,
+    headers: HeaderMap,
+) -> Response {
+    info!("API: Listing voicemail for user ID: {}", id);
+
+    let status = match query.status.as_deref().map(parse_status) {
+        Some(None) => {
+            return (
+                StatusCode::BAD_REQUEST,
+                Json(ApiResponse::<()>::error(format!(
+                    "Unknown voicemail status: {}",
+                    query.status.unwrap_or_default()
+                ))),
+            )
+                .into_response()
+        }
+        Some(status) => status,
+        None => None,
+    };
+
+    let access = match open_mailbox(&state, &headers, id, "list").await {
+        Ok(access) => access,
+        Err(response) => return response,
+    };
+    access.audit("list", None);
+
+    let listed_deleted = status == Some(VoicemailStatus::Deleted);
+    match access.repository.list_messages(&access.mailbox, status).await {
+        Ok(messages) => {
+            let messages: Vec<VoicemailDto> = messages
+                .into_iter()
+                .filter(|m| listed_deleted || m.status != VoicemailStatus::Deleted)
+                .map(Into::into)
+                .collect();
+            Json(ApiResponse::success(messages)).into_response()
+        }
+        Err(e) => {
+            error!("API: Failed to list voicemail: {}", e);
+            StatusCode::INTERNAL_SERVER_ERROR.into_response()
+        }
+    }
+}
+
+/// Stream a message's recording; a `Range` header gets `206 Partial Content`
+pub async fn get_voicemail_audio(
+    State(state): State<AppState>,
+    Path((id, message_id)): Path<(i32, Uuid)>,
+    headers: HeaderMap,
+) -> Response {
+    let access = match open_mailbox(&state, &headers, id, "play").await {
+        Ok(access) => access,
+        Err(response) => return response,
+    };
+    let message = match access.message(message_id).await {
+        Ok(message) => message,
+        Err(response) => return response,
+    };
+    let Some(dir) = voicemail_dir(&state) else {
+        return unavailable("Voicemail storage");
+    };
+    access.audit("play", Some(message_id));
+
+    let path = dir.join(&message.audio_file_path);
+    let mut file = match tokio::fs::File::open(&path).await {
+        Ok(file) => file,
+        Err(e) => {
+            error!("API: Failed to open recording {}: {}", path.display(), e);
+            return not_found(format!("Recording of voicemail {} not found", message_id));
+        }
+    };
+    let len = match file.metadata().await {
+        Ok(metadata) => metadata.len(),
+        Err(e) => {
+            error!("API: Failed to read recording {}: {}", path.display(), e);
+            return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+        }
+    };
+
+    let range = headers.get(header::RANGE).and_then(|v| v.to_str().ok());
+    let (status, first, last) = match byte_range(range, len) {
+        ByteRange::Full => (StatusCode::OK, 0, len.saturating_sub(1)),
+        ByteRange::Partial(first, last) => (StatusCode::PARTIAL_CONTENT, first, last),
+        ByteRange::Unsatisfiable => {
+            return (
+                StatusCode::RANGE_NOT_SATISFIABLE,
+                [(header::CONTENT_RANGE, format!("bytes */{}", len))],
+            )
+                .into_response()
+        }
+    };
+    let body_len = if len == 0 { 0 } else { last - first + 1 };
+    if first > 0 {
+        if let Err(e) = file.seek(SeekFrom::Start(first)).await {
+            error!("API: Failed to seek recording {}: {}", path.display(), e);
+            return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+        }
+    }
+
+    let mut response = Response::builder()
+        .status(status)
+        .header(header::CONTENT_TYPE, audio_content_type(&message.audio_format))
+        .header(header::CONTENT_LENGTH, body_len)
+        .header(header::ACCEPT_RANGES, "bytes")
+        .header(header::CACHE_CONTROL, AUDIO_CACHE_CONTROL)
+        .header(header::ETAG, format!("\"{}\"", message.id));
+    if status == StatusCode::PARTIAL_CONTENT {
+        response = response.header(
+            header::CONTENT_RANGE,
+            format!("bytes {}-{}/{}", first, last, len),
+        );
+    }
+    response
+        .body(Body::from_stream(stream_file(file, body_len)))
+        .unwrap()
+}
+
+/// Mark a message read or unread; the owner's lamp follows
+pub async fn update_voicemail(
+    State(state): State<AppState>,
+    Path((id, message_id)): Path<(i32, Uuid)>,
+    headers: HeaderMap,
+    Json(request): Json<UpdateVoicemailRequest>,
+) -> Response {
+    let access = match open_mailbox(&state, &headers, id, "update").await {
+        Ok(access) => access,
+        Err(response) => return response,
+    };
+    if let Err(response) = access.message(message_id).await {
+        return response;
+    }
+    access.audit(if request.read { "mark_read" } else { "mark_unread" }, Some(message_id));
+
+    let status = if request.read {
+        VoicemailStatus::Read
+    } else {
+        VoicemailStatus::New
+    };
+    if let Err(e) = access.repository.update_message_status(message_id, status).await {
+        error!("API: Failed to update voicemail {}: {}", message_id, e);
+        return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+    }
+
+    match access.message(message_id).await {
+        Ok(message) => Json(ApiResponse::success(VoicemailDto::from(message))).into_response(),
+        Err(response) => response,
+    }
+}
+
+/// Delete a message, and its recording unless other copies still use it
+pub async fn delete_voicemail(
+    State(state): State<AppState>,
+    Path((id, message_id)): Path<(i32, Uuid)>,
+    headers: HeaderMap,
+) -> Response {
+    let access = match open_mailbox(&state, &headers, id, "delete").await {
+        Ok(access) => access,
+        Err(response) => return response,
+    };
+    let message = match access.message(message_id).await {
+        Ok(message) => message,
+        Err(response) => return response,
+    };
+    access.audit("delete", Some(message_id));
+
+    // Without a voicemail directory the recording cannot be found; only
+    // the message goes
+    let result = match voicemail_dir(&state) {
+        Some(dir) => {
+            VoicemailService::new(dir)
+                .delete_message(access.repository.as_ref(), &message)
+                .await
+        }
+        None => access.repository.delete_message(message_id).await,
+    };
+    match result {
+        Ok(()) => StatusCode::NO_CONTENT.into_response(),
+        Err(e) => {
+            error!("API: Failed to delete voicemail {}: {}", message_id, e);
+            StatusCode::INTERNAL_SERVER_ERROR.into_response()
+        }
+    }
+}
+
+/// Forward a copy of a message to another mailbox; the copy shares the
+/// recording and arrives unread
+pub async fn forward_voicemail(
+    State(state): State<AppState>,
+    Path((id, message_id)): Path<(i32, Uuid)>,
+    headers: HeaderMap,
+    Json(request): Json<ForwardVoicemailRequest>,
+) -> Response {
+    let access = match open_mailbox(&state, &headers, id, "forward").await {
+        Ok(access) => access,
+        Err(response) => return response,
+    };
+    let message = match access.message(message_id).await {
+        Ok(message) => message,
+        Err(response) => return response,
+    };
+
+    match state.user_repository.find_by_username(&request.mailbox).await {
+        Ok(Some(_)) => {}
+        Ok(None) => return not_found(format!("Mailbox {} not found", request.mailbox)),
+        Err(e) => {
+            error!("API: Failed to get user {}: {}", request.mailbox, e);
+            return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+        }
+    }
+    access.audit(&format!("forward:{}", request.mailbox), Some(message_id));
+
+    match access.repository.create_message(message.forward_to(request.mailbox)).await {
+        Ok(copy) => (
+            StatusCode::CREATED,
+            Json(ApiResponse::success(VoicemailDto::from(copy))),
+        )
+            .into_response(),
+        Err(e) => {
+            error!("API: Failed to forward voicemail {}: {}", message_id, e);
+            StatusCode::INTERNAL_SERVER_ERROR.into_response()
+        }
+    }
+}
+
+/// Part of a recording asked for with a `Range` header
+#[derive(Debug, PartialEq, Eq)]
+enum ByteRange {
+    /// No (usable) range: the whole file
+    Full,
+    /// First and last byte, inclusive
+    Partial(u64, u64),
+    Unsatisfiable,
+}
+
+/// Range of a `bytes=` header over a `len`-byte file
+///
+/// Only single ranges are served; multiple ranges and malformed headers
+/// get the whole file, as RFC 9110 allows.
+fn byte_range(header: Option<&str>, len: u64) -> ByteRange {
+    let Some(spec) = header.and_then(|h| h.trim().strip_prefix("bytes=")) else {
+        return ByteRange::Full;
+    };
+    if spec.contains(',') {
+        return ByteRange::Full;
+    }
+    let Some((first, last)) = spec.split_once('-') else {
+        return ByteRange::Full;
+    };
+
+    match (first.trim(), last.trim()) {
+        ("", "") => ByteRange::Full,
+        // Last `n` bytes
+        ("", suffix) => match suffix.parse::<u64>() {
+            Ok(0) => ByteRange::Unsatisfiable,
+            Ok(_) if len == 0 => ByteRange::Unsatisfiable,
+            Ok(n) => ByteRange::Partial(len - n.min(len), len - 1),
+            Err(_) => ByteRange::Full,
+        },
+        (first, last) => {
+            let Ok(first) = first.parse::<u64>() else {
+                return ByteRange::Full;
+            };
+            let last = match last {
+                "" => len.saturating_sub(1),
+                last => match last.parse::<u64>() {
+                    Ok(last) if last >= first => last.min(len.saturating_sub(1)),
+                    _ => return ByteRange::Full,
+                },
+            };
+            if first >= len {
+                ByteRange::Unsatisfiable
+            } else {
+                ByteRange::Partial(first, last)
+            }
+        }
+    }
+}
+
+/// MIME type of a recording format
+fn audio_content_type(format: &str) -> &'static str {
+    match format.to_lowercase().as_str() {
+        "wav" => "audio/wav",
+        "mp3" => "audio/mpeg",
+        "ogg" | "opus" => "audio/ogg",
+        _ => "application/octet-stream",
+    }
+}
+
+/// The next `remaining` bytes of `file`, read a chunk at a time so large
+/// recordings are never held in memory
+fn stream_file(
+    file: tokio::fs::File,
+    remaining: u64,
+) -> impl Stream<Item = Result<Bytes, io::Error>> + Send {
+    futures::stream::unfold((file, remaining), |(mut file, remaining)| async move {
+        if remaining == 0 {
+            return None;
+        }
+        let mut chunk = vec![0u8; remaining.min(AUDIO_CHUNK_BYTES as u64) as usize];
+        match file.read(&mut chunk).await {
+            Ok(0) => None,
+            Ok(n) => {
+                chunk.truncate(n);
+                Some((Ok(Bytes::from(chunk)), (file, remaining - n as u64)))
+            }
+            Err(e) => Some((Err(e), (file, 0))),
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Config;
+    use crate::domain::user::repository::MockUserRepository;
+    use crate::domain::user::role_repository::MockRoleRepository;
+    use crate::domain::user::{Role, User};
+    use crate::infrastructure::persistence::MemoryVoicemailRepository;
+    use crate::infrastructure::protocols::sip::transport::OutgoingMessage;
+    use crate::infrastructure::protocols::sip::{MwiNotifier, MwiVoicemailRepository, Registrar};
+    use crate::infrastructure::storage::{StorageConfig, StorageGuard};
+    use base64::Engine;
+    use tokio::sync::mpsc;
+
+    fn user(id: i32, username: &str, role: &Role) -> User {
+        User {
+            id,
+            username: username.to_string(),
+            password_hash: String::new(),
+            sip_ha1: None,
+            realm: "example.com".to_string(),
+            display_name: None,
+            email: None,
+            department: None,
+            locale: None,
+            timezone: None,
+            enabled: true,
+            role_id: Some(role.id),
+            created_at: Utc::now(),
+            updated_at: Utc::now(),
+        }
+    }
+
+    struct Fixture {
+        state: AppState,
+        registrar: Arc<Registrar>,
+        notifies: mpsc::Receiver<OutgoingMessage>,
+        dir: PathBuf,
+    }
+
+    /// Alice (1) and bob (2) are standard users, carol (3) an administrator;
+    /// the repository sends MWI updates as in production
+    fn fixture() -> Fixture {
+        let standard = Role::user();
+        let administrator = Role::administrator();
+        let users = [
+            user(1, "alice", &standard),
+            user(2, "bob", &standard),
+            user(3, "carol", &administrator),
+        ];
+
+        let mut user_repository = MockUserRepository::new();
+        let known = users.clone();
+        user_repository
+            .expect_verify_credentials()
+            .returning(move |username, password| {
+                Ok(known
+                    .iter()
+                    .find(|u| u.username == username && password == "secret")
+                    .cloned())
+            });
+        let known = users.clone();
+        user_repository
+            .expect_find_by_id()
+            .returning(move |id| Ok(known.iter().find(|u| u.id == id).cloned()));
+        let known = users.clone();
+        user_repository
+            .expect_find_by_username()
+            .returning(move |username| Ok(known.iter().find(|u| u.username == username).cloned()));
+        let mut roles = MockRoleRepository::new();
+        roles.expect_get_by_id().returning(move |id| {
+            Ok([&standard, &administrator]
+                .into_iter()
+                .find(|role| role.id == id)
+                .cloned())
+        });
+
+        let registrar = Arc::new(Registrar::new());
+        let store: Arc<dyn VoicemailRepository> = Arc::new(MemoryVoicemailRepository::new());
+        let (tx, notifies) = mpsc::channel(16);
+        let notifier = Arc::new(MwiNotifier::new(
+            registrar.clone(),
+            store.clone(),
+            tx,
+            "example.com".to_string(),
+            "192.0.2.1:5060".to_string(),
+        ));
+
+        let dir = std::env::temp_dir().join(format!("yakyak-vm-api-{}", Uuid::new_v4()));
+        let mut storage = StorageConfig::default();
+        storage.voicemail.dir = Some(dir.clone());
+
+        let mut state = AppState::for_tests(Arc::new(user_repository));
+        state.voicemail_repository = Some(Arc::new(MwiVoicemailRepository::new(store, notifier)));
+        state.storage = Some(Arc::new(StorageGuard::new(storage)));
+        state.diagnostics = Some(Arc::new(
+            DiagnosticsContext::new(&Config::default()).with_role_repository(Arc::new(roles)),
+        ));
+        Fixture {
+            state,
+            registrar,
+            notifies,
+            dir,
+        }
+    }
+
+    fn credentials(username: &str) -> HeaderMap {
+        let encoded = base64::engine::general_purpose::STANDARD.encode(format!("{}:secret", username));
+        let mut headers = HeaderMap::new();
+        headers.insert(header::AUTHORIZATION, format!("Basic {}", encoded).parse().unwrap());
+        headers
+    }
+
+    /// A message in `mailbox` whose recording is bytes 0..=255 repeated
+    async fn deposit(fixture: &Fixture, mailbox: &str, len: usize) -> VoicemailMessage {
+        let file = format!("{}/msg_{}.wav", mailbox, Uuid::new_v4());
+        let path = fixture.dir.join(&file);
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+        let audio: Vec<u8> = (0..len).map(|i| (i % 256) as u8).collect();
+        std::fs::write(&path, audio).unwrap();
+
+        let message = VoicemailMessage::new(
+            mailbox.to_string(),
+            "sip:dave@example.com".to_string(),
+            None,
+            12,
+            file,
+            "wav".to_string(),
+        );
+        let repository = fixture.state.voicemail_repository.clone().unwrap();
+        repository.create_message(message).await.unwrap()
+    }
+
+    async fn body(response: Response) -> Vec<u8> {
+        axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap()
+            .to_vec()
+    }
+
+    #[test]
+    fn test_byte_range() {
+        assert_eq!(byte_range(None, 1000), ByteRange::Full);
+        assert_eq!(byte_range(Some("bytes=0-99"), 1000), ByteRange::Partial(0, 99));
+        assert_eq!(byte_range(Some("bytes=900-"), 1000), ByteRange::Partial(900, 999));
+        assert_eq!(byte_range(Some("bytes=-100"), 1000), ByteRange::Partial(900, 999));
+        assert_eq!(byte_range(Some("bytes=-5000"), 1000), ByteRange::Partial(0, 999));
+        assert_eq!(byte_range(Some("bytes=990-5000"), 1000), ByteRange::Partial(990, 999));
+        assert_eq!(byte_range(Some("bytes=1000-"), 1000), ByteRange::Unsatisfiable);
+        assert_eq!(byte_range(Some("bytes=-0"), 1000), ByteRange::Unsatisfiable);
+        // Multiple, reversed and malformed ranges get the whole file
+        assert_eq!(byte_range(Some("bytes=0-1,5-9"), 1000), ByteRange::Full);
+        assert_eq!(byte_range(Some("bytes=9-5"), 1000), ByteRange::Full);
+        assert_eq!(byte_range(Some("items=0-9"), 1000), ByteRange::Full);
+    }
+
+    #[tokio::test]
+    async fn test_audio_range_requests() {
+        let fixture = fixture();
+        let message = deposit(&fixture, "alice", 200_000).await;
+        let audio: Vec<u8> = (0..200_000).map(|i| (i % 256) as u8).collect();
+        let play = |headers: HeaderMap| {
+            get_voicemail_audio(
+                State(fixture.state.clone()),
+                Path((1, message.id)),
+                headers,
+            )
+        };
+
+        let response = play(credentials("alice")).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.headers()[header::CONTENT_TYPE], "audio/wav");
+        assert_eq!(response.headers()[header::ACCEPT_RANGES], "bytes");
+        assert_eq!(response.headers()[header::CONTENT_LENGTH], "200000");
+        assert_eq!(response.headers()[header::CACHE_CONTROL], AUDIO_CACHE_CONTROL);
+        assert_eq!(body(response).await, audio);
+
+        // A range spanning chunk boundaries comes back byte for byte
+        let mut headers = credentials("alice");
+        headers.insert(header::RANGE, "bytes=65530-131080".parse().unwrap());
+        let response = play(headers).await;
+        assert_eq!(response.status(), StatusCode::PARTIAL_CONTENT);
+        assert_eq!(response.headers()[header::CONTENT_RANGE], "bytes 65530-131080/200000");
+        assert_eq!(response.headers()[header::CONTENT_LENGTH], "65551");
+        assert_eq!(body(response).await, &audio[65530..=131080]);
+
+        let mut headers = credentials("alice");
+        headers.insert(header::RANGE, "bytes=-10".parse().unwrap());
+        let response = play(headers).await;
+        assert_eq!(response.headers()[header::CONTENT_RANGE], "bytes 199990-199999/200000");
+        assert_eq!(body(response).await, &audio[199_990..]);
+
+        let mut headers = credentials("alice");
+        headers.insert(header::RANGE, "bytes=200000-".parse().unwrap());
+        let response = play(headers).await;
+        assert_eq!(response.status(), StatusCode::RANGE_NOT_SATISFIABLE);
+        assert_eq!(response.headers()[header::CONTENT_RANGE], "bytes */200000");
+
+        std::fs::remove_dir_all(&fixture.dir).ok();
+    }
+
+    #[tokio::test]
+    async fn test_mark_read_updates_lamp() {
+        let mut fixture = fixture();
+        fixture
+            .registrar
+            .add_binding("sip:alice@example.com".to_string(), "sip:alice@10.0.0.7:5062".to_string(), 3600)
+            .await
+            .unwrap();
+        let message = deposit(&fixture, "alice", 100).await;
+        while fixture.notifies.try_recv().is_ok() {}
+
+        let response = update_voicemail(
+            State(fixture.state.clone()),
+            Path((1, message.id)),
+            credentials("alice"),
+            Json(UpdateVoicemailRequest { read: true }),
+        )
+        .await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let notify = fixture.notifies.try_recv().unwrap();
+        let text = String::from_utf8_lossy(&notify.data).to_string();
+        assert!(text.contains("Messages-Waiting: no"));
+        assert!(text.contains("Voice-Message: 0/1"));
+
+        // Unread again: the lamp comes back on
+        update_voicemail(
+            State(fixture.state.clone()),
+            Path((1, message.id)),
+            credentials("alice"),
+            Json(UpdateVoicemailRequest { read: false }),
+        )
+        .await;
+        let text = String::from_utf8_lossy(&fixture.notifies.try_recv().unwrap().data).to_string();
+        assert!(text.contains("Messages-Waiting: yes"));
+        assert!(text.contains("Voice-Message: 1/0"));
+
+        std::fs::remove_dir_all(&fixture.dir).ok();
+    }
+
+    #[tokio::test]
+    async fn test_other_mailbox_forbidden() {
+        let fixture = fixture();
+        let message = deposit(&fixture, "alice", 100).await;
+        let state = || State(fixture.state.clone());
+
+        // Bob may not list, play, mark or delete alice's messages
+        let response = list_user_voicemail(
+            state(),
+            Path(1),
+            Query(VoicemailListQuery { status: None }),
+            credentials("bob"),
+        )
+        .await;
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+        let response = get_voicemail_audio(state(), Path((1, message.id)), credentials("bob")).await;
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+        let response = delete_voicemail(state(), Path((1, message.id)), credentials("bob")).await;
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+        // Nor reach them through his own mailbox
+        let response = get_voicemail_audio(state(), Path((2, message.id)), credentials("bob")).await;
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+        let response = get_voicemail_audio(state(), Path((1, message.id)), HeaderMap::new()).await;
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+
+        // An administrator with voicemail:manage may
+        let response = list_user_voicemail(
+            state(),
+            Path(1),
+            Query(VoicemailListQuery { status: None }),
+            credentials("carol"),
+        )
+        .await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let listed: serde_json::Value = serde_json::from_slice(&body(response).await).unwrap();
+        assert_eq!(listed["data"][0]["id"], message.id.to_string());
+
+        std::fs::remove_dir_all(&fixture.dir).ok();
+    }
+
+    #[tokio::test]
+    async fn test_forward_and_delete() {
+        let fixture = fixture();
+        let message = deposit(&fixture, "alice", 100).await;
+        let repository = fixture.state.voicemail_repository.clone().unwrap();
+
+        let response = forward_voicemail(
+            State(fixture.state.clone()),
+            Path((1, message.id)),
+            credentials("alice"),
+            Json(ForwardVoicemailRequest { mailbox: "bob".to_string() }),
+        )
+        .await;
+        assert_eq!(response.status(), StatusCode::CREATED);
+        let copies = repository.list_messages("bob", None).await.unwrap();
+        assert_eq!(copies.len(), 1);
+        assert_eq!(copies[0].status, VoicemailStatus::New);
+        assert_eq!(copies[0].audio_file_path, message.audio_file_path);
+
+        let response = forward_voicemail(
+            State(fixture.state.clone()),
+            Path((1, message.id)),
+            credentials("alice"),
+            Json(ForwardVoicemailRequest { mailbox: "nobody".to_string() }),
+        )
+        .await;
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+
+        // The recording stays while bob's copy uses it
+        let path = fixture.dir.join(&message.audio_file_path);
+        let response =
+            delete_voicemail(State(fixture.state.clone()), Path((1, message.id)), credentials("alice")).await;
+        assert_eq!(response.status(), StatusCode::NO_CONTENT);
+        assert!(repository.get_message(message.id).await.unwrap().is_none());
+        assert!(path.exists());
+
+        let response =
+            delete_voicemail(State(fixture.state.clone()), Path((2, copies[0].id)), credentials("bob")).await;
+        assert_eq!(response.status(), StatusCode::NO_CONTENT);
+        assert!(!path.exists());
+
+        std::fs::remove_dir_all(&fixture.dir).ok();
+    }
+}
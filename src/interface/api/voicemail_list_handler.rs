@@ -0,0 +1,159 @@
+//! Voicemail distribution list API handlers
+//!
+//! Lists live under `/api/voicemail/lists`. A message sent to a list number
+//! from the voicemail menu is copied into every member mailbox; changing
+//! the members does not touch messages already delivered.
+
+use super::cdr_dto::ApiResponse;
+use super::user_handler::AppState;
+use crate::domain::voicemail_list::{
+    ListSendPermission, VoicemailDistributionList, VoicemailListError, VoicemailListService,
+    DEFAULT_MAX_MEMBERS,
+};
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    Json,
+};
+use serde::Deserialize;
+use std::sync::Arc;
+use tracing::{error, info};
+use uuid::Uuid;
+
+/// Create/update distribution list request
+#[derive(Debug, Deserialize)]
+pub struct VoicemailListRequest {
+    pub name: String,
+    pub number: String,
+    pub owner: String,
+    #[serde(default)]
+    pub members: Vec<String>,
+    #[serde(default = "default_max_members")]
+    pub max_members: u32,
+    #[serde(default)]
+    pub senders: ListSendPermission,
+}
+
+fn default_max_members() -> u32 {
+    DEFAULT_MAX_MEMBERS
+}
+
+impl VoicemailListRequest {
+    fn into_list(self) -> VoicemailDistributionList {
+        VoicemailDistributionList {
+            members: self.members,
+            max_members: self.max_members,
+            senders: self.senders,
+            ..VoicemailDistributionList::new(self.name, self.number, self.owner)
+        }
+    }
+}
+
+fn service_unavailable() -> Response {
+    error!("Voicemail list service not available");
+    (
+        StatusCode::SERVICE_UNAVAILABLE,
+        Json(ApiResponse::<()>::error(
+            "Voicemail distribution lists not available".to_string(),
+        )),
+    )
+        .into_response()
+}
+
+#[allow(clippy::result_large_err)]
+fn service(state: &AppState) -> Result<&Arc<VoicemailListService>, Response> {
+    state.voicemail_lists.as_ref().ok_or_else(service_unavailable)
+}
+
+fn error_response(e: VoicemailListError) -> Response {
+    let status = match e {
+        VoicemailListError::Invalid(_) => StatusCode::BAD_REQUEST,
+        VoicemailListError::NotFound(_) | VoicemailListError::UnknownNumber(_) => {
+            StatusCode::NOT_FOUND
+        }
+        VoicemailListError::Duplicate(_) => StatusCode::CONFLICT,
+        VoicemailListError::NotAllowed { .. } => StatusCode::FORBIDDEN,
+        VoicemailListError::Repository(ref e) => {
+            error!("API: Voicemail list database error: {}", e);
+            return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+        }
+    };
+    (status, Json(ApiResponse::<()>::error(e.to_string()))).into_response()
+}
+
+/// List distribution lists
+pub async fn list_voicemail_lists(State(state): State<AppState>) -> Response {
+    let service = match service(&state) {
+        Ok(service) => service,
+        Err(response) => return response,
+    };
+    match service.list().await {
+        Ok(lists) => Json(ApiResponse::success(lists)).into_response(),
+        Err(e) => error_response(e),
+    }
+}
+
+/// Get a distribution list
+pub async fn get_voicemail_list(
+    State(state): State<AppState>,
+    Path(id): Path<Uuid>,
+) -> Response {
+    let service = match service(&state) {
+        Ok(service) => service,
+        Err(response) => return response,
+    };
+    match service.get(id).await {
+        Ok(list) => Json(ApiResponse::success(list)).into_response(),
+        Err(e) => error_response(e),
+    }
+}
+
+/// Create a distribution list
+pub async fn create_voicemail_list(
+    State(state): State<AppState>,
+    Json(req): Json<VoicemailListRequest>,
+) -> Response {
+    let service = match service(&state) {
+        Ok(service) => service,
+        Err(response) => return response,
+    };
+    info!("API: Creating voicemail list {} ({})", req.name, req.number);
+    match service.create(req.into_list()).await {
+        Ok(list) => (StatusCode::CREATED, Json(ApiResponse::success(list))).into_response(),
+        Err(e) => error_response(e),
+    }
+}
+
+/// Replace a distribution list's settings and members
+pub async fn update_voicemail_list(
+    State(state): State<AppState>,
+    Path(id): Path<Uuid>,
+    Json(req): Json<VoicemailListRequest>,
+) -> Response {
+    let service = match service(&state) {
+        Ok(service) => service,
+        Err(response) => return response,
+    };
+    info!("API: Updating voicemail list {}", id);
+    match service.update(id, req.into_list()).await {
+        Ok(list) => Json(ApiResponse::success(list)).into_response(),
+        Err(e) => error_response(e),
+    }
+}
+
+/// Delete a distribution list
+pub async fn delete_voicemail_list(
+    State(state): State<AppState>,
+    Path(id): Path<Uuid>,
+) -> Response {
+    let service = match service(&state) {
+        Ok(service) => service,
+        Err(response) => return response,
+    };
+    info!("API: Deleting voicemail list {}", id);
+    match service.delete(id).await {
+        Ok(()) => StatusCode::NO_CONTENT.into_response(),
+        Err(e) => error_response(e),
+    }
+}
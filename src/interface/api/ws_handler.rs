@@ -1,16 +1,50 @@
 //! WebSocket event streaming handler
 
+use super::cdr_dto::ApiResponse;
+use super::diagnostics_handler::{authenticate, client_ip};
+use super::user_handler::AppState;
+use super::ws_protocol::{
+    Command, CommandError, CommandErrorCode, CommandRequest, CommandResult, ControlMessage,
+    WS_PROTOCOL_VERSION,
+};
+use crate::application::chat::{ChatNotice, ChatSession};
+use crate::application::events::EventBus;
+use crate::application::scheduled_call::{ScheduledCallEvent, ScheduledCallService};
+use crate::config::CallControlConfig;
+use crate::domain::call::CallEvent;
+use crate::domain::call_queue::{AgentStateChange, AgentStateReason};
+use crate::domain::call_queue_engine::CallQueueEngine;
+use crate::domain::conference::FloorEvent;
+use crate::domain::conference_manager::ConferenceManager;
+use crate::domain::fraud_detection::{FraudAlert, FraudDetector};
+use crate::domain::queue_reporting::QueueSnapshot;
+use crate::domain::security::{SecurityEvent, SecuritySeverity};
+use crate::domain::user::Permission;
+use crate::infrastructure::media::{BandwidthMonitor, CapacityEvent, CapacityMonitor, QualityWarning};
+use crate::infrastructure::setup_latency::{SetupLatencyMonitor, SloEvent};
+use crate::infrastructure::storage::{StorageEvent, StorageGuard};
+use crate::infrastructure::transcription::{CaptionEvent, LiveTranscriptionService};
+use crate::infrastructure::protocols::sip::{
+    CallRouter, CodecFallback, CodecFallbackEvent, MaintenanceEvent, MaintenanceRegistry, Registrar, RegistrationEvent,
+    RegistrationEventType, SurvivabilityEvent, SurvivabilityManager, TransferSession, WarmTransferManager,
+};
 use axum::{
     extract::{
         ws::{Message, WebSocket, WebSocketUpgrade},
         State,
     },
-    response::Response,
+    http::{header, HeaderMap, StatusCode},
+    response::{IntoResponse, Response},
+    Json,
 };
 use serde::{Deserialize, Serialize};
+use std::collections::{HashSet, VecDeque};
 use std::sync::Arc;
-use tokio::sync::broadcast;
-use tracing::{debug, error, info};
+use std::time::{Duration, Instant};
+use tokio::sync::{broadcast, mpsc};
+use tokio::task::JoinHandle;
+use tracing::{debug, error, info, warn};
+use uuid::Uuid;
 
 /// Event types that can be broadcast to WebSocket clients
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -42,6 +76,38 @@ pub enum Event {
     },
     /// User unregistered event
     UserUnregistered { aor: String },
+    /// Registrar binding event (added/refreshed/expired/removed)
+    RegistrationChanged(RegistrationEvent),
+    /// Registration churn detected for an AoR
+    RegistrationChurn(RegistrationEvent),
+    /// Outbound call violated a fraud rule
+    FraudAlert(FraudAlert),
+    /// Periodic wallboard view of all running queues
+    QueueWallboard { queues: Vec<QueueSnapshot> },
+    /// Queue agent paused, resumed or logged out
+    AgentStateChanged(AgentStateChange),
+    /// Hand raised or floor granted/released in a lecture mode conference
+    ConferenceFloor(FloorEvent),
+    /// New calls switched between the normal and constrained codec profile
+    CapacityChanged(CapacityEvent),
+    /// Tenant or trunk put into or out of maintenance, or drained
+    MaintenanceChanged(MaintenanceEvent),
+    /// Branch node lost its upstream, or got it back
+    SurvivabilityChanged(SurvivabilityEvent),
+    /// Storage threshold crossed, write refused or old files deleted
+    StorageAlert(StorageEvent),
+    /// Call setup latency SLO breached or recovered
+    SloAlert(SloEvent),
+    /// Caption text from a live transcribed call
+    Caption(CaptionEvent),
+    /// Call moved off Opus after decode errors, or concealed when it could not be
+    CodecFallback(CodecFallbackEvent),
+    /// WebRTC leg's bandwidth estimate fell below a usable bitrate
+    BandwidthWarning(QualityWarning),
+    /// Warm transfer started, answered by its target or settled
+    WarmTransfer(TransferSession),
+    /// Scheduled call answered, confirmed or failed
+    ScheduledCall(ScheduledCallEvent),
     /// Active calls count updated
     ActiveCallsUpdated { count: usize },
     /// Registered users count updated
@@ -84,44 +150,798 @@ impl Default for EventBroadcaster {
     }
 }
 
+/// Publish registrar events on the broadcaster
+pub fn forward_registration_events(
+    registrar: &Registrar,
+    broadcaster: Arc<EventBroadcaster>,
+) -> JoinHandle<()> {
+    let mut rx = registrar.subscribe_events();
+    tokio::spawn(async move {
+        loop {
+            match rx.recv().await {
+                Ok(event) => {
+                    let event = if event.event_type == RegistrationEventType::Churn {
+                        Event::RegistrationChurn(event)
+                    } else {
+                        Event::RegistrationChanged(event)
+                    };
+                    broadcaster.publish(event);
+                }
+                Err(broadcast::error::RecvError::Lagged(skipped)) => {
+                    warn!("Dropped {} registration events (subscriber lagging)", skipped);
+                }
+                Err(broadcast::error::RecvError::Closed) => break,
+            }
+        }
+    })
+}
+
+/// Publish fraud alerts on the broadcaster
+pub fn forward_fraud_alerts(
+    fraud_detector: &FraudDetector,
+    broadcaster: Arc<EventBroadcaster>,
+) -> JoinHandle<()> {
+    let mut rx = fraud_detector.subscribe();
+    tokio::spawn(async move {
+        loop {
+            match rx.recv().await {
+                Ok(alert) => broadcaster.publish(Event::FraudAlert(alert)),
+                Err(broadcast::error::RecvError::Lagged(skipped)) => {
+                    warn!("Dropped {} fraud alerts (subscriber lagging)", skipped);
+                }
+                Err(broadcast::error::RecvError::Closed) => break,
+            }
+        }
+    })
+}
+
+/// Publish call lifecycle events on the broadcaster
+pub fn forward_call_events(bus: &dyn EventBus, broadcaster: Arc<EventBroadcaster>) -> JoinHandle<()> {
+    let mut rx = bus.subscribe();
+    tokio::spawn(async move {
+        loop {
+            match rx.recv().await {
+                Ok(envelope) => {
+                    let call_id = envelope.call_id;
+                    let state_change = |old_state: &str, new_state: &str| Event::CallStateChanged {
+                        call_id: call_id.clone(),
+                        old_state: old_state.to_string(),
+                        new_state: new_state.to_string(),
+                    };
+                    let event = match envelope.event {
+                        CallEvent::Initiated(e) => Event::CallInitiated {
+                            call_id: call_id.clone(),
+                            caller_uri: e.caller.uri().to_string(),
+                            callee_uri: e.callee.uri().to_string(),
+                        },
+                        CallEvent::Ringing(_) => state_change("initiating", "ringing"),
+                        CallEvent::Answered(_) => state_change("ringing", "answered"),
+                        CallEvent::Held(_) => state_change("answered", "on_hold"),
+                        // Billing milestones, not state changes
+                        CallEvent::EarlyMedia(_) | CallEvent::Acknowledged(_) => continue,
+                        CallEvent::Resumed(_) => state_change("on_hold", "answered"),
+                        CallEvent::Ended(e) => Event::CallEnded {
+                            call_id: call_id.clone(),
+                            duration: e.duration_seconds.unwrap_or(0),
+                            reason: format!("{:?}", e.reason),
+                        },
+                    };
+                    broadcaster.publish(event);
+                }
+                Err(broadcast::error::RecvError::Lagged(skipped)) => {
+                    warn!("Dropped {} call events (subscriber lagging)", skipped);
+                }
+                Err(broadcast::error::RecvError::Closed) => break,
+            }
+        }
+    })
+}
+
+/// Publish queue agent availability changes on the broadcaster
+pub fn forward_agent_state_changes(
+    engine: &CallQueueEngine,
+    broadcaster: Arc<EventBroadcaster>,
+) -> JoinHandle<()> {
+    let mut rx = engine.subscribe_agent_states();
+    tokio::spawn(async move {
+        loop {
+            match rx.recv().await {
+                Ok(change) => broadcaster.publish(Event::AgentStateChanged(change)),
+                Err(broadcast::error::RecvError::Lagged(skipped)) => {
+                    warn!("Dropped {} agent state changes (subscriber lagging)", skipped);
+                }
+                Err(broadcast::error::RecvError::Closed) => break,
+            }
+        }
+    })
+}
+
+/// Publish conference floor changes on the broadcaster
+pub fn forward_floor_events(
+    manager: &ConferenceManager,
+    broadcaster: Arc<EventBroadcaster>,
+) -> JoinHandle<()> {
+    let mut rx = manager.subscribe_floor_events();
+    tokio::spawn(async move {
+        loop {
+            match rx.recv().await {
+                Ok(event) => broadcaster.publish(Event::ConferenceFloor(event)),
+                Err(broadcast::error::RecvError::Lagged(skipped)) => {
+                    warn!("Dropped {} floor events (subscriber lagging)", skipped);
+                }
+                Err(broadcast::error::RecvError::Closed) => break,
+            }
+        }
+    })
+}
+
+/// Publish codec profile changes on the broadcaster
+pub fn forward_capacity_events(
+    monitor: &CapacityMonitor,
+    broadcaster: Arc<EventBroadcaster>,
+) -> JoinHandle<()> {
+    let mut rx = monitor.subscribe();
+    tokio::spawn(async move {
+        loop {
+            match rx.recv().await {
+                Ok(event) => broadcaster.publish(Event::CapacityChanged(event)),
+                Err(broadcast::error::RecvError::Lagged(skipped)) => {
+                    warn!("Dropped {} capacity events (subscriber lagging)", skipped);
+                }
+                Err(broadcast::error::RecvError::Closed) => break,
+            }
+        }
+    })
+}
+
+/// Publish maintenance changes on the broadcaster
+pub fn forward_maintenance_events(
+    registry: &MaintenanceRegistry,
+    broadcaster: Arc<EventBroadcaster>,
+) -> JoinHandle<()> {
+    let mut rx = registry.subscribe();
+    tokio::spawn(async move {
+        loop {
+            match rx.recv().await {
+                Ok(event) => broadcaster.publish(Event::MaintenanceChanged(event)),
+                Err(broadcast::error::RecvError::Lagged(skipped)) => {
+                    warn!("Dropped {} maintenance events (subscriber lagging)", skipped);
+                }
+                Err(broadcast::error::RecvError::Closed) => break,
+            }
+        }
+    })
+}
+
+/// Publish survivability changes on the broadcaster
+pub fn forward_survivability_events(
+    survivability: &SurvivabilityManager,
+    broadcaster: Arc<EventBroadcaster>,
+) -> JoinHandle<()> {
+    let mut rx = survivability.subscribe();
+    tokio::spawn(async move {
+        loop {
+            match rx.recv().await {
+                Ok(event) => broadcaster.publish(Event::SurvivabilityChanged(event)),
+                Err(broadcast::error::RecvError::Lagged(skipped)) => {
+                    warn!("Dropped {} survivability events (subscriber lagging)", skipped);
+                }
+                Err(broadcast::error::RecvError::Closed) => break,
+            }
+        }
+    })
+}
+
+/// Publish storage alerts on the broadcaster
+pub fn forward_storage_events(
+    guard: &StorageGuard,
+    broadcaster: Arc<EventBroadcaster>,
+) -> JoinHandle<()> {
+    let mut rx = guard.subscribe();
+    tokio::spawn(async move {
+        loop {
+            match rx.recv().await {
+                Ok(event) => broadcaster.publish(Event::StorageAlert(event)),
+                Err(broadcast::error::RecvError::Lagged(skipped)) => {
+                    warn!("Dropped {} storage events (subscriber lagging)", skipped);
+                }
+                Err(broadcast::error::RecvError::Closed) => break,
+            }
+        }
+    })
+}
+
+/// Publish call setup SLO breaches and recoveries on the broadcaster
+pub fn forward_slo_events(
+    monitor: &SetupLatencyMonitor,
+    broadcaster: Arc<EventBroadcaster>,
+) -> JoinHandle<()> {
+    let mut rx = monitor.subscribe();
+    tokio::spawn(async move {
+        loop {
+            match rx.recv().await {
+                Ok(event) => broadcaster.publish(Event::SloAlert(event)),
+                Err(broadcast::error::RecvError::Lagged(skipped)) => {
+                    warn!("Dropped {} SLO events (subscriber lagging)", skipped);
+                }
+                Err(broadcast::error::RecvError::Closed) => break,
+            }
+        }
+    })
+}
+
+/// Publish the outcomes of scheduled calls
+pub fn forward_scheduled_call_events(
+    service: &ScheduledCallService,
+    broadcaster: Arc<EventBroadcaster>,
+) -> JoinHandle<()> {
+    let mut rx = service.subscribe();
+    tokio::spawn(async move {
+        loop {
+            match rx.recv().await {
+                Ok(event) => broadcaster.publish(Event::ScheduledCall(event)),
+                Err(broadcast::error::RecvError::Lagged(skipped)) => {
+                    warn!("Dropped {} scheduled call events (subscriber lagging)", skipped);
+                }
+                Err(broadcast::error::RecvError::Closed) => break,
+            }
+        }
+    })
+}
+
+/// Publish captions of live transcribed calls
+pub fn forward_captions(
+    service: &LiveTranscriptionService,
+    broadcaster: Arc<EventBroadcaster>,
+) -> JoinHandle<()> {
+    let mut rx = service.subscribe();
+    tokio::spawn(async move {
+        loop {
+            match rx.recv().await {
+                Ok(event) => broadcaster.publish(Event::Caption(event)),
+                Err(broadcast::error::RecvError::Lagged(skipped)) => {
+                    warn!("Dropped {} captions (subscriber lagging)", skipped);
+                }
+                Err(broadcast::error::RecvError::Closed) => break,
+            }
+        }
+    })
+}
+
+/// Publish codec fallbacks of calls with undecodable media
+pub fn forward_codec_fallbacks(
+    fallback: &CodecFallback,
+    broadcaster: Arc<EventBroadcaster>,
+) -> JoinHandle<()> {
+    let mut rx = fallback.subscribe();
+    tokio::spawn(async move {
+        loop {
+            match rx.recv().await {
+                Ok(event) => broadcaster.publish(Event::CodecFallback(event)),
+                Err(broadcast::error::RecvError::Lagged(skipped)) => {
+                    warn!("Dropped {} codec fallback events (subscriber lagging)", skipped);
+                }
+                Err(broadcast::error::RecvError::Closed) => break,
+            }
+        }
+    })
+}
+
+/// Publish warnings about legs capped below a usable bitrate
+pub fn forward_bandwidth_warnings(
+    monitor: &BandwidthMonitor,
+    broadcaster: Arc<EventBroadcaster>,
+) -> JoinHandle<()> {
+    let mut rx = monitor.subscribe();
+    tokio::spawn(async move {
+        loop {
+            match rx.recv().await {
+                Ok(warning) => broadcaster.publish(Event::BandwidthWarning(warning)),
+                Err(broadcast::error::RecvError::Lagged(skipped)) => {
+                    warn!("Dropped {} bandwidth warnings (subscriber lagging)", skipped);
+                }
+                Err(broadcast::error::RecvError::Closed) => break,
+            }
+        }
+    })
+}
+
+/// Publish each change of a warm transfer
+pub fn forward_warm_transfers(
+    manager: &WarmTransferManager,
+    broadcaster: Arc<EventBroadcaster>,
+) -> JoinHandle<()> {
+    let mut rx = manager.subscribe();
+    tokio::spawn(async move {
+        loop {
+            match rx.recv().await {
+                Ok(session) => broadcaster.publish(Event::WarmTransfer(session)),
+                Err(broadcast::error::RecvError::Lagged(skipped)) => {
+                    warn!("Dropped {} warm transfer events (subscriber lagging)", skipped);
+                }
+                Err(broadcast::error::RecvError::Closed) => break,
+            }
+        }
+    })
+}
+
+/// Publish a queue wallboard snapshot every `interval`
+pub fn publish_queue_wallboard(
+    engine: Arc<CallQueueEngine>,
+    broadcaster: Arc<EventBroadcaster>,
+    interval: std::time::Duration,
+) -> JoinHandle<()> {
+    tokio::spawn(async move {
+        let mut ticker = tokio::time::interval(interval);
+        loop {
+            ticker.tick().await;
+            // Nobody is watching
+            if broadcaster.subscriber_count() == 0 {
+                continue;
+            }
+            let queues = engine.snapshots();
+            if !queues.is_empty() {
+                broadcaster.publish(Event::QueueWallboard { queues });
+            }
+        }
+    })
+}
+
+/// State of the WebSocket route
+#[derive(Clone)]
+pub struct WsState {
+    broadcaster: Arc<EventBroadcaster>,
+    /// Services commands are run through, as for the REST API
+    app: AppState,
+}
+
+impl WsState {
+    pub fn new(broadcaster: Arc<EventBroadcaster>, app: AppState) -> Self {
+        Self { broadcaster, app }
+    }
+}
+
+/// User a connection authenticated as
+struct ControlUser {
+    username: String,
+    realm: String,
+    /// The user's own address; calls are dialed from it
+    aor: String,
+    permissions: HashSet<Permission>,
+}
+
+/// Commands a connection sent within the window
+struct CommandRateLimiter {
+    sent: VecDeque<Instant>,
+    max_commands: usize,
+    window: Duration,
+}
+
+impl CommandRateLimiter {
+    fn new(config: &CallControlConfig) -> Self {
+        Self {
+            sent: VecDeque::new(),
+            max_commands: config.max_commands,
+            window: Duration::from_secs(config.window_secs),
+        }
+    }
+
+    fn allow(&mut self) -> bool {
+        let now = Instant::now();
+        while self
+            .sent
+            .front()
+            .is_some_and(|sent| now.duration_since(*sent) >= self.window)
+        {
+            self.sent.pop_front();
+        }
+        if self.sent.len() < self.max_commands {
+            self.sent.push_back(now);
+            true
+        } else {
+            false
+        }
+    }
+}
+
+/// Runs the commands of one connection
+struct CallControl {
+    app: AppState,
+    /// None when the socket was opened without credentials
+    user: Option<ControlUser>,
+    ip: String,
+    limiter: CommandRateLimiter,
+    /// Open while the user's tenant has chat enabled
+    chat: Option<ChatSession>,
+}
+
+/// What a successful command produced
+enum Outcome {
+    Done,
+    /// Call placed by a `dial`
+    Dialed(String),
+    /// Message sent by a `send_message`
+    Sent(Uuid),
+}
+
+impl CallControl {
+    fn new(app: AppState, user: Option<ControlUser>, ip: String) -> Self {
+        let limiter = CommandRateLimiter::new(&app.call_control);
+        Self {
+            app,
+            user,
+            ip,
+            limiter,
+            chat: None,
+        }
+    }
+
+    /// Open the user's chat session; its notices arrive on the receiver
+    async fn open_chat(&mut self) -> Option<mpsc::Receiver<ChatNotice>> {
+        let user = self.user.as_ref()?;
+        let chat = self.app.chat.as_ref()?;
+        if !chat.enabled_for(&user.realm) {
+            return None;
+        }
+        let (session, rx) = chat.connect(&user.username).await;
+        self.chat = Some(session);
+        Some(rx)
+    }
+
+    fn hello(&self) -> ControlMessage {
+        ControlMessage::Hello {
+            version: WS_PROTOCOL_VERSION,
+            username: self.user.as_ref().map(|user| user.username.clone()),
+        }
+    }
+
+    /// Run a command message; the result is correlated by its request id
+    async fn handle(&mut self, text: &str) -> CommandResult {
+        let request: CommandRequest = match serde_json::from_str(text) {
+            Ok(request) => request,
+            Err(e) => {
+                // Still correlate the error when the request id is readable
+                let request_id = serde_json::from_str::<serde_json::Value>(text)
+                    .ok()
+                    .and_then(|v| v.get("request_id")?.as_str().map(str::to_string))
+                    .unwrap_or_default();
+                return CommandResult::failure(
+                    request_id,
+                    CommandError::new(
+                        CommandErrorCode::InvalidRequest,
+                        format!("Invalid command: {}", e),
+                    ),
+                );
+            }
+        };
+
+        let request_id = request.request_id.clone();
+        match self.run(request).await {
+            Ok(Outcome::Done) => CommandResult::success(request_id, None),
+            Ok(Outcome::Dialed(call_id)) => CommandResult::success(request_id, Some(call_id)),
+            Ok(Outcome::Sent(message_id)) => CommandResult {
+                message_id: Some(message_id),
+                ..CommandResult::success(request_id, None)
+            },
+            Err(error) => CommandResult::failure(request_id, error),
+        }
+    }
+
+    async fn run(&mut self, request: CommandRequest) -> Result<Outcome, CommandError> {
+        if request.version != WS_PROTOCOL_VERSION {
+            return Err(CommandError::new(
+                CommandErrorCode::UnsupportedVersion,
+                format!(
+                    "Protocol version {} is not supported (server speaks {})",
+                    request.version, WS_PROTOCOL_VERSION
+                ),
+            ));
+        }
+        let Some(user) = &self.user else {
+            return Err(CommandError::new(
+                CommandErrorCode::Unauthenticated,
+                "Authentication required",
+            ));
+        };
+        if !self.limiter.allow() {
+            warn!("WebSocket: {} is sending commands too fast", user.username);
+            return Err(CommandError::new(
+                CommandErrorCode::RateLimited,
+                "Too many commands",
+            ));
+        }
+
+        let command = request.command;
+        let permission = command.permission();
+        if !user.permissions.contains(&permission) {
+            return Err(self.deny(user, command.name(), permission));
+        }
+
+        info!(
+            "WebSocket: {} sent {} (request {})",
+            user.username,
+            command.name(),
+            request.request_id
+        );
+        self.execute(user, command).await
+    }
+
+    async fn execute(
+        &self,
+        user: &ControlUser,
+        command: Command,
+    ) -> Result<Outcome, CommandError> {
+        let failed = |e: String| CommandError::new(CommandErrorCode::Failed, e);
+        match command {
+            Command::Dial { to } => {
+                let callee = if to.contains(':') {
+                    to
+                } else {
+                    format!("sip:{}@{}", to, user.realm)
+                };
+                self.router()?
+                    .originate(&user.aor, &callee)
+                    .await
+                    .map(Outcome::Dialed)
+                    .map_err(failed)
+            }
+            Command::Answer { call_id } => {
+                self.router()?.answer_call(&call_id).await.map_err(failed)?;
+                Ok(Outcome::Done)
+            }
+            Command::Hold { call_id } => {
+                self.router()?
+                    .hold_call_from(&call_id, &user.aor)
+                    .await
+                    .map_err(failed)?;
+                Ok(Outcome::Done)
+            }
+            Command::Resume { call_id } => {
+                self.router()?.resume_call(&call_id).await.map_err(failed)?;
+                Ok(Outcome::Done)
+            }
+            Command::Transfer { call_id, target } => {
+                self.router()?
+                    .blind_transfer(&call_id, &target)
+                    .await
+                    .map_err(failed)?;
+                Ok(Outcome::Done)
+            }
+            Command::Hangup { call_id } => {
+                self.router()?.hangup_call(&call_id).await.map_err(failed)?;
+                Ok(Outcome::Done)
+            }
+            Command::SetAgentState {
+                queue_id,
+                member_id,
+                state,
+            } => {
+                let engine = self.app.queue_engine.as_ref().ok_or_else(|| {
+                    CommandError::new(CommandErrorCode::Unavailable, "Queue engine not available")
+                })?;
+                let member = engine
+                    .get_member(queue_id, member_id)
+                    .map_err(|e| failed(e.to_string()))?;
+                // Supervisors change the state of other agents
+                if member.username != user.username
+                    && !user.permissions.contains(&Permission::SystemConfig)
+                {
+                    return Err(self.deny(user, "set_agent_state", Permission::SystemConfig));
+                }
+                engine
+                    .set_agent_availability(queue_id, member_id, state, AgentStateReason::Manual)
+                    .map_err(|e| failed(e.to_string()))?;
+                Ok(Outcome::Done)
+            }
+            Command::GrantFloor {
+                room_id,
+                participant_id,
+            } => {
+                self.conferences()?
+                    .grant_floor(room_id, participant_id)
+                    .await
+                    .map_err(failed)?;
+                Ok(Outcome::Done)
+            }
+            Command::RevokeFloor {
+                room_id,
+                participant_id,
+            } => {
+                self.conferences()?
+                    .revoke_floor(room_id, participant_id)
+                    .await
+                    .map_err(failed)?;
+                Ok(Outcome::Done)
+            }
+            Command::SendMessage { to, text } => {
+                let message = self
+                    .chat()?
+                    .service()
+                    .send(&user.username, &user.realm, &to, text)
+                    .await
+                    .map_err(failed)?;
+                Ok(Outcome::Sent(message.id))
+            }
+            Command::MarkRead { message_id } => {
+                self.chat()?
+                    .service()
+                    .mark_read(&user.username, message_id)
+                    .await
+                    .map_err(failed)?;
+                Ok(Outcome::Done)
+            }
+            Command::SetPresence { state } => {
+                self.chat()?
+                    .service()
+                    .set_presence(&user.username, state)
+                    .map_err(failed)?;
+                Ok(Outcome::Done)
+            }
+            Command::SubscribePresence { users } => {
+                self.chat()?.watch(users);
+                Ok(Outcome::Done)
+            }
+        }
+    }
+
+    fn router(&self) -> Result<&Arc<CallRouter>, CommandError> {
+        self.app.call_router.as_ref().ok_or_else(|| {
+            CommandError::new(CommandErrorCode::Unavailable, "Call router not available")
+        })
+    }
+
+    fn conferences(&self) -> Result<&Arc<ConferenceManager>, CommandError> {
+        self.app.conference_manager.as_ref().ok_or_else(|| {
+            CommandError::new(CommandErrorCode::Unavailable, "Conference service not available")
+        })
+    }
+
+    /// The connection's chat session; there is none when chat is off for
+    /// the user's tenant
+    fn chat(&self) -> Result<&ChatSession, CommandError> {
+        self.chat.as_ref().ok_or_else(|| {
+            CommandError::new(CommandErrorCode::Unavailable, "Chat not available")
+        })
+    }
+
+    /// Refuse a command the user lacks `permission` for
+    fn deny(&self, user: &ControlUser, action: &str, permission: Permission) -> CommandError {
+        warn!("WebSocket: {} denied {}", user.username, action);
+        if let Some(diagnostics) = &self.app.diagnostics {
+            diagnostics.audit(
+                SecurityEvent::PermissionDenied {
+                    username: user.username.clone(),
+                    ip: self.ip.clone(),
+                    resource: "call_control".to_string(),
+                    action: action.to_string(),
+                },
+                SecuritySeverity::Medium,
+            );
+        }
+        CommandError::new(
+            CommandErrorCode::Forbidden,
+            format!("Permission {} required", permission.as_str()),
+        )
+    }
+}
+
 /// WebSocket handler
+///
+/// Clients that send HTTP Basic credentials can also send call control
+/// commands (see [`super::ws_protocol`]); the others only receive events.
 pub async fn ws_handler(
     ws: WebSocketUpgrade,
-    State(broadcaster): State<Arc<EventBroadcaster>>,
+    State(state): State<WsState>,
+    headers: HeaderMap,
 ) -> Response {
-    ws.on_upgrade(|socket| handle_socket(socket, broadcaster))
+    let user = if headers.contains_key(header::AUTHORIZATION) {
+        let Some(diagnostics) = state.app.diagnostics.clone() else {
+            error!("Authentication not available");
+            return (
+                StatusCode::SERVICE_UNAVAILABLE,
+                Json(ApiResponse::<()>::error(
+                    "Authentication not available".to_string(),
+                )),
+            )
+                .into_response();
+        };
+        match authenticate(&state.app, &diagnostics, &headers).await {
+            Ok((user, permissions)) => Some(ControlUser {
+                aor: format!("sip:{}@{}", user.username, user.realm),
+                username: user.username,
+                realm: user.realm,
+                permissions,
+            }),
+            Err(response) => return response,
+        }
+    } else {
+        None
+    };
+
+    let control = CallControl::new(state.app.clone(), user, client_ip(&headers));
+    ws.on_upgrade(|socket| handle_socket(socket, state.broadcaster, control))
+}
+
+/// Send `message` as JSON; only a failed send is an error
+async fn send_json<T: Serialize>(
+    sender: &mut SplitSink<WebSocket, Message>,
+    message: &T,
+) -> Result<(), axum::Error> {
+    match serde_json::to_string(message) {
+        Ok(json) => sender.send(Message::Text(json)).await,
+        Err(e) => {
+            error!("Failed to serialize message: {}", e);
+            Ok(())
+        }
+    }
+}
+
+/// Next chat notice; never ready without a chat session
+async fn next_notice(rx: &mut Option<mpsc::Receiver<ChatNotice>>) -> Option<ChatNotice> {
+    match rx {
+        Some(rx) => rx.recv().await,
+        None => std::future::pending().await,
+    }
 }
 
 /// Handle WebSocket connection
-async fn handle_socket(socket: WebSocket, broadcaster: Arc<EventBroadcaster>) {
+async fn handle_socket(
+    socket: WebSocket,
+    broadcaster: Arc<EventBroadcaster>,
+    mut control: CallControl,
+) {
     let (mut sender, mut receiver) = socket.split();
     let mut rx = broadcaster.subscribe();
+    let (reply_tx, mut reply_rx) = mpsc::channel::<ControlMessage>(16);
+    let mut chat_rx = control.open_chat().await;
+    let hello = control.hello();
 
     info!("WebSocket client connected");
 
-    // Spawn a task to send events to the client
+    // Spawn a task to send events and command results to the client
     let mut send_task = tokio::spawn(async move {
-        while let Ok(event) = rx.recv().await {
-            match serde_json::to_string(&event) {
-                Ok(json) => {
-                    if sender.send(Message::Text(json)).await.is_err() {
-                        debug!("Failed to send event to WebSocket client");
-                        break;
+        if send_json(&mut sender, &hello).await.is_err() {
+            return;
+        }
+        loop {
+            let sent = tokio::select! {
+                event = rx.recv() => match event {
+                    Ok(event) => send_json(&mut sender, &event).await,
+                    Err(broadcast::error::RecvError::Lagged(skipped)) => {
+                        warn!("Dropped {} events for a WebSocket client (lagging)", skipped);
+                        Ok(())
                     }
-                }
-                Err(e) => {
-                    error!("Failed to serialize event: {}", e);
-                }
+                    Err(broadcast::error::RecvError::Closed) => break,
+                },
+                reply = reply_rx.recv() => match reply {
+                    Some(reply) => send_json(&mut sender, &reply).await,
+                    None => break,
+                },
+                notice = next_notice(&mut chat_rx) => match notice {
+                    Some(notice) => send_json(&mut sender, &ControlMessage::from(notice)).await,
+                    None => break,
+                },
+            };
+            if sent.is_err() {
+                debug!("Failed to send to WebSocket client");
+                break;
             }
         }
     });
 
-    // Spawn a task to receive messages from the client (for heartbeat/ping)
+    // Spawn a task to receive commands (and heartbeats) from the client
     let mut recv_task = tokio::spawn(async move {
         while let Some(Ok(msg)) = receiver.next().await {
             match msg {
                 Message::Text(text) => {
-                    debug!("Received text message: {}", text);
+                    let result = control.handle(&text).await;
+                    if reply_tx
+                        .send(ControlMessage::CommandResult(result))
+                        .await
+                        .is_err()
+                    {
+                        break;
+                    }
                 }
                 Message::Ping(_) => {
                     debug!("Received ping");
@@ -151,4 +971,354 @@ async fn handle_socket(socket: WebSocket, broadcaster: Arc<EventBroadcaster>) {
 }
 
 // Need to import futures StreamExt for split() and SinkExt for send()
+use futures::stream::SplitSink;
 use futures::{SinkExt, StreamExt};
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::application::call::CallApplicationService;
+    use crate::application::chat::{spawn_call_presence, ChatConfig, ChatService};
+    use crate::config::Config;
+    use crate::domain::user::repository::MockUserRepository;
+    use crate::domain::user::role_repository::MockRoleRepository;
+    use crate::domain::user::{Role, User};
+    use crate::infrastructure::messaging::InProcessEventBus;
+    use crate::infrastructure::persistence::MemoryMessageRepository;
+    use crate::interface::api::diagnostics_handler::DiagnosticsContext;
+    use axum::{routing::get, Router};
+    use base64::Engine;
+    use serde_json::{json, Value};
+    use tokio_tungstenite::tungstenite::{self, client::IntoClientRequest};
+
+    fn user(username: &str, role: &Role) -> User {
+        User {
+            id: 1,
+            username: username.to_string(),
+            password_hash: String::new(),
+            sip_ha1: None,
+            realm: "example.com".to_string(),
+            display_name: None,
+            email: None,
+            department: None,
+            locale: None,
+            timezone: None,
+            enabled: true,
+            role_id: Some(role.id),
+            created_at: chrono::Utc::now(),
+            updated_at: chrono::Utc::now(),
+        }
+    }
+
+    /// Alice is an operator, bob a standard user
+    fn app_state(router: CallRouter) -> AppState {
+        let operator = Role::operator();
+        let standard = Role::user();
+        let alice = user("alice", &operator);
+        let bob = user("bob", &standard);
+
+        let mut users = MockUserRepository::new();
+        users
+            .expect_verify_credentials()
+            .returning(move |username, password| {
+                Ok(match (username, password) {
+                    ("alice", "secret") => Some(alice.clone()),
+                    ("bob", "secret") => Some(bob.clone()),
+                    _ => None,
+                })
+            });
+        let mut roles = MockRoleRepository::new();
+        roles.expect_get_by_id().returning(move |id| {
+            Ok([&operator, &standard]
+                .into_iter()
+                .find(|role| role.id == id)
+                .cloned())
+        });
+
+        let mut state = AppState::for_tests(Arc::new(users));
+        state.call_router = Some(Arc::new(router));
+        state.diagnostics = Some(Arc::new(
+            DiagnosticsContext::new(&Config::default()).with_role_repository(Arc::new(roles)),
+        ));
+        state
+    }
+
+    async fn connected(state: &AppState, username: &str) -> CallControl {
+        let credentials =
+            base64::engine::general_purpose::STANDARD.encode(format!("{}:secret", username));
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            header::AUTHORIZATION,
+            format!("Basic {}", credentials).parse().unwrap(),
+        );
+        let diagnostics = state.diagnostics.clone().unwrap();
+        let (user, permissions) = authenticate(state, &diagnostics, &headers).await.unwrap();
+        let user = ControlUser {
+            aor: format!("sip:{}@{}", user.username, user.realm),
+            username: user.username,
+            realm: user.realm,
+            permissions,
+        };
+        CallControl::new(state.clone(), Some(user), "127.0.0.1".to_string())
+    }
+
+    fn error_code(result: &CommandResult) -> Option<CommandErrorCode> {
+        result.error.as_ref().map(|e| e.code)
+    }
+
+    #[tokio::test]
+    async fn test_command_checks() {
+        let router = CallRouter::new(Arc::new(Registrar::new()));
+        let mut state = app_state(router);
+        state.call_control = CallControlConfig {
+            max_commands: 2,
+            window_secs: 60,
+        };
+        let hangup = json!({"version": 1, "request_id": "1", "command": "hangup", "call_id": "c1"});
+
+        // Events only without credentials
+        let mut anonymous = CallControl::new(state.clone(), None, "127.0.0.1".to_string());
+        let result = anonymous.handle(&hangup.to_string()).await;
+        assert_eq!(error_code(&result), Some(CommandErrorCode::Unauthenticated));
+
+        // Standard users may not hang up calls
+        let mut bob = connected(&state, "bob").await;
+        let result = bob.handle(&hangup.to_string()).await;
+        assert_eq!(result.request_id, "1");
+        assert_eq!(error_code(&result), Some(CommandErrorCode::Forbidden));
+
+        let mut alice = connected(&state, "alice").await;
+        let result = alice
+            .handle(r#"{"version": 2, "request_id": "2", "command": "hangup", "call_id": "c1"}"#)
+            .await;
+        assert_eq!(error_code(&result), Some(CommandErrorCode::UnsupportedVersion));
+        let result = alice.handle(r#"{"request_id": "3", "command": "fly"}"#).await;
+        assert_eq!(result.request_id, "3");
+        assert_eq!(error_code(&result), Some(CommandErrorCode::InvalidRequest));
+
+        // The call does not exist; then the window is used up
+        let result = alice.handle(&hangup.to_string()).await;
+        assert_eq!(error_code(&result), Some(CommandErrorCode::Failed));
+        let result = alice.handle(&hangup.to_string()).await;
+        assert_eq!(error_code(&result), Some(CommandErrorCode::Failed));
+        let result = alice.handle(&hangup.to_string()).await;
+        assert_eq!(error_code(&result), Some(CommandErrorCode::RateLimited));
+    }
+
+    type Client =
+        tokio_tungstenite::WebSocketStream<tokio_tungstenite::MaybeTlsStream<tokio::net::TcpStream>>;
+
+    /// Messages from the server, kept until looked for: events and results
+    /// are interleaved in no fixed order
+    struct Inbox {
+        client: Client,
+        received: Vec<Value>,
+    }
+
+    impl Inbox {
+        async fn send(&mut self, command: Value) {
+            self.client
+                .send(tungstenite::Message::Text(command.to_string()))
+                .await
+                .unwrap();
+        }
+
+        async fn wait_for(&mut self, pred: impl Fn(&Value) -> bool) -> Value {
+            if let Some(i) = self.received.iter().position(&pred) {
+                return self.received.remove(i);
+            }
+            tokio::time::timeout(Duration::from_secs(5), async {
+                loop {
+                    let message = self.client.next().await.unwrap().unwrap();
+                    if let tungstenite::Message::Text(text) = message {
+                        let value: Value = serde_json::from_str(&text).unwrap();
+                        if pred(&value) {
+                            return value;
+                        }
+                        self.received.push(value);
+                    }
+                }
+            })
+            .await
+            .expect("message not received")
+        }
+    }
+
+    fn is_result(request_id: &'static str) -> impl Fn(&Value) -> bool {
+        move |v| v["type"] == "CommandResult" && v["data"]["request_id"] == request_id
+    }
+
+    /// Serve `/ws` on an ephemeral port
+    async fn serve(broadcaster: Arc<EventBroadcaster>, state: AppState) -> std::net::SocketAddr {
+        let app = Router::new()
+            .route("/ws", get(ws_handler))
+            .with_state(WsState::new(broadcaster, state));
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+        addr
+    }
+
+    /// Connect as `username` and wait for the hello
+    async fn open(addr: std::net::SocketAddr, username: &str) -> Inbox {
+        let mut request = format!("ws://{}/ws", addr).into_client_request().unwrap();
+        let credentials =
+            base64::engine::general_purpose::STANDARD.encode(format!("{}:secret", username));
+        request.headers_mut().insert(
+            header::AUTHORIZATION,
+            format!("Basic {}", credentials).parse().unwrap(),
+        );
+        let (client, _) = tokio_tungstenite::connect_async(request).await.unwrap();
+        let mut inbox = Inbox {
+            client,
+            received: Vec::new(),
+        };
+        let hello = inbox.wait_for(|v| v["type"] == "Hello").await;
+        assert_eq!(hello["data"]["username"], username);
+        assert_eq!(hello["data"]["version"], WS_PROTOCOL_VERSION);
+        inbox
+    }
+
+    fn chat_service() -> Arc<ChatService> {
+        Arc::new(ChatService::new(
+            ChatConfig::default(),
+            Arc::new(MemoryMessageRepository::new()),
+        ))
+    }
+
+    #[tokio::test]
+    async fn test_dial_answer_hangup_over_websocket() {
+        let bus = Arc::new(InProcessEventBus::default());
+        let broadcaster = Arc::new(EventBroadcaster::new());
+        forward_call_events(bus.as_ref(), broadcaster.clone());
+        let router = CallRouter::new(Arc::new(Registrar::new()))
+            .with_call_events(Arc::new(CallApplicationService::new(bus.clone())));
+        let addr = serve(broadcaster, app_state(router)).await;
+        let mut inbox = open(addr, "alice").await;
+
+        inbox
+            .send(json!({"version": 1, "request_id": "dial-1", "command": "dial", "to": "bob"}))
+            .await;
+        let dialed = inbox.wait_for(is_result("dial-1")).await;
+        assert_eq!(dialed["data"]["ok"], true, "{}", dialed);
+        let call_id = dialed["data"]["call_id"].as_str().unwrap().to_string();
+
+        let is_call_event = |event: &'static str, state: Option<&'static str>| {
+            let call_id = call_id.clone();
+            move |v: &Value| {
+                v["type"] == event
+                    && v["data"]["call_id"] == call_id.as_str()
+                    && state.is_none_or(|state| v["data"]["new_state"] == state)
+            }
+        };
+        let initiated = inbox.wait_for(is_call_event("CallInitiated", None)).await;
+        assert_eq!(initiated["data"]["caller_uri"], "sip:alice@example.com");
+        assert_eq!(initiated["data"]["callee_uri"], "sip:bob@example.com");
+        inbox
+            .wait_for(is_call_event("CallStateChanged", Some("ringing")))
+            .await;
+
+        inbox
+            .send(json!({"version": 1, "request_id": "answer-1", "command": "answer", "call_id": call_id}))
+            .await;
+        let answered = inbox.wait_for(is_result("answer-1")).await;
+        assert_eq!(answered["data"]["ok"], true, "{}", answered);
+        inbox
+            .wait_for(is_call_event("CallStateChanged", Some("answered")))
+            .await;
+
+        inbox
+            .send(json!({"version": 1, "request_id": "hangup-1", "command": "hangup", "call_id": call_id}))
+            .await;
+        let hung_up = inbox.wait_for(is_result("hangup-1")).await;
+        assert_eq!(hung_up["data"]["ok"], true, "{}", hung_up);
+        inbox.wait_for(is_call_event("CallEnded", None)).await;
+    }
+
+    #[tokio::test]
+    async fn test_chat_with_receipts_over_websocket() {
+        let mut state = app_state(CallRouter::new(Arc::new(Registrar::new())));
+        state.chat = Some(chat_service());
+        let addr = serve(Arc::new(EventBroadcaster::new()), state).await;
+        let mut alice = open(addr, "alice").await;
+        let mut bob = open(addr, "bob").await;
+
+        alice
+            .send(json!({"version": 1, "request_id": "msg-1", "command": "send_message", "to": "bob", "text": "Can you take 5001?"}))
+            .await;
+        let sent = alice.wait_for(is_result("msg-1")).await;
+        assert_eq!(sent["data"]["ok"], true, "{}", sent);
+        let message_id = sent["data"]["message_id"].as_str().unwrap().to_string();
+
+        let is_message = |v: &Value| v["type"] == "ChatMessage";
+        let received = bob.wait_for(is_message).await;
+        assert_eq!(received["data"]["id"], message_id.as_str());
+        assert_eq!(received["data"]["from"], "alice");
+        assert_eq!(received["data"]["text"], "Can you take 5001?");
+
+        let is_receipt = |status: &'static str| {
+            let message_id = message_id.clone();
+            move |v: &Value| {
+                v["type"] == "ChatReceipt"
+                    && v["data"]["message_id"] == message_id.as_str()
+                    && v["data"]["status"] == status
+            }
+        };
+        let delivered = alice.wait_for(is_receipt("delivered")).await;
+        assert_eq!(delivered["data"]["by"], "bob");
+
+        bob.send(json!({"version": 1, "request_id": "read-1", "command": "mark_read", "message_id": message_id}))
+            .await;
+        let read = bob.wait_for(is_result("read-1")).await;
+        assert_eq!(read["data"]["ok"], true, "{}", read);
+        alice.wait_for(is_receipt("read")).await;
+
+        // Only the recipient reads a message
+        alice
+            .send(json!({"version": 1, "request_id": "read-2", "command": "mark_read", "message_id": message_id}))
+            .await;
+        let refused = alice.wait_for(is_result("read-2")).await;
+        assert_eq!(refused["data"]["error"]["code"], "failed");
+    }
+
+    #[tokio::test]
+    async fn test_active_call_shows_on_call_presence() {
+        let bus = Arc::new(InProcessEventBus::default());
+        let router = CallRouter::new(Arc::new(Registrar::new()))
+            .with_call_events(Arc::new(CallApplicationService::new(bus.clone())));
+        let mut state = app_state(router);
+        let router = state.call_router.clone().unwrap();
+        let chat = Arc::new(
+            ChatService::new(ChatConfig::default(), Arc::new(MemoryMessageRepository::new()))
+                .with_call_router(router.clone()),
+        );
+        spawn_call_presence(chat.clone(), bus.as_ref());
+        state.chat = Some(chat);
+        let addr = serve(Arc::new(EventBroadcaster::new()), state).await;
+        let _alice = open(addr, "alice").await;
+        let mut bob = open(addr, "bob").await;
+
+        let is_presence = |state: &'static str| {
+            move |v: &Value| {
+                v["type"] == "Presence"
+                    && v["data"]["username"] == "alice"
+                    && v["data"]["state"] == state
+            }
+        };
+        bob.send(json!({"version": 1, "request_id": "sub-1", "command": "subscribe_presence", "users": ["alice"]}))
+            .await;
+        let subscribed = bob.wait_for(is_result("sub-1")).await;
+        assert_eq!(subscribed["data"]["ok"], true, "{}", subscribed);
+        bob.wait_for(is_presence("available")).await;
+
+        let call_id = router
+            .originate("sip:alice@example.com", "sip:carol@example.com")
+            .await
+            .unwrap();
+        router.answer_call(&call_id).await.unwrap();
+        bob.wait_for(is_presence("on_call")).await;
+
+        router.hangup_call(&call_id).await.unwrap();
+        bob.wait_for(is_presence("available")).await;
+    }
+}
@@ -0,0 +1,377 @@
+//! WebSocket call control protocol
+//!
+//! Besides receiving the event stream, clients that authenticated when the
+//! socket was opened (e.g. agent desktops) can control calls by sending
+//! commands on `/ws`. Every command carries the protocol version and a
+//! request id chosen by the client; its result comes back between the
+//! events as a `CommandResult` with the same request id:
+//!
+//! ```text
+//! -> {"version":1,"request_id":"7","command":"hold","call_id":"a84b4c76e66710"}
+//! <- {"type":"CommandResult","data":{"version":1,"request_id":"7","ok":true}}
+//! ```
+//!
+//! The same connection carries internal chat: messages, receipts and the
+//! presence of followed users arrive as `ChatMessage`, `ChatReceipt` and
+//! `Presence` messages.
+
+use crate::application::chat::{ChatMessage, ChatNotice, ChatPresence, ChatReceipt, PresenceUpdate};
+use crate::domain::call_queue::AgentAvailability;
+use crate::domain::user::Permission;
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+/// Version of the command protocol spoken by the server
+pub const WS_PROTOCOL_VERSION: u32 = 1;
+
+/// A command sent by a client
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct CommandRequest {
+    /// Protocol version the client speaks
+    pub version: u32,
+    /// Echoed in the result
+    pub request_id: String,
+    #[serde(flatten)]
+    pub command: Command,
+}
+
+/// Call control commands
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "command", rename_all = "snake_case")]
+pub enum Command {
+    /// Call `to` (a URI or a user of the caller's realm) as the
+    /// authenticated user
+    Dial { to: String },
+    /// Answer a call that has not been answered yet
+    Answer { call_id: String },
+    Hold { call_id: String },
+    Resume { call_id: String },
+    /// Blind transfer to `target`
+    Transfer { call_id: String, target: String },
+    Hangup { call_id: String },
+    /// Pause, resume or log out a queue agent; agents may only change
+    /// their own state unless they have `system:config`
+    SetAgentState {
+        queue_id: Uuid,
+        member_id: Uuid,
+        state: AgentAvailability,
+    },
+    /// Let a participant of a lecture mode conference talk
+    GrantFloor { room_id: Uuid, participant_id: Uuid },
+    /// Mute a speaker of a lecture mode conference again
+    RevokeFloor { room_id: Uuid, participant_id: Uuid },
+    /// Chat with another user
+    SendMessage { to: String, text: String },
+    /// Tell the sender a received message was read
+    MarkRead { message_id: Uuid },
+    /// Available, away or do-not-disturb
+    SetPresence { state: ChatPresence },
+    /// Follow the presence of `users`
+    SubscribePresence { users: Vec<String> },
+}
+
+impl Command {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Dial { .. } => "dial",
+            Self::Answer { .. } => "answer",
+            Self::Hold { .. } => "hold",
+            Self::Resume { .. } => "resume",
+            Self::Transfer { .. } => "transfer",
+            Self::Hangup { .. } => "hangup",
+            Self::SetAgentState { .. } => "set_agent_state",
+            Self::GrantFloor { .. } => "grant_floor",
+            Self::RevokeFloor { .. } => "revoke_floor",
+            Self::SendMessage { .. } => "send_message",
+            Self::MarkRead { .. } => "mark_read",
+            Self::SetPresence { .. } => "set_presence",
+            Self::SubscribePresence { .. } => "subscribe_presence",
+        }
+    }
+
+    /// Permission the connection's user needs to send the command
+    pub fn permission(&self) -> Permission {
+        match self {
+            Self::Dial { .. } | Self::Answer { .. } => Permission::CallCreate,
+            Self::Hold { .. } | Self::Resume { .. } | Self::Transfer { .. } => {
+                Permission::CallTransfer
+            }
+            Self::Hangup { .. } => Permission::CallTerminate,
+            Self::SetAgentState { .. }
+            | Self::SendMessage { .. }
+            | Self::MarkRead { .. }
+            | Self::SetPresence { .. }
+            | Self::SubscribePresence { .. } => Permission::CallRead,
+            Self::GrantFloor { .. } | Self::RevokeFloor { .. } => {
+                Permission::ConferenceModerate
+            }
+        }
+    }
+}
+
+/// Messages the server sends besides events
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", content = "data")]
+pub enum ControlMessage {
+    /// Sent once when the socket opens; `username` is None for clients
+    /// that did not authenticate and can only receive events
+    Hello {
+        version: u32,
+        username: Option<String>,
+    },
+    CommandResult(CommandResult),
+    /// Chat message to the connection's user
+    ChatMessage(ChatMessage),
+    /// A message the connection's user sent was delivered or read
+    ChatReceipt(ChatReceipt),
+    /// Presence of a followed user
+    Presence(PresenceUpdate),
+}
+
+impl From<ChatNotice> for ControlMessage {
+    fn from(notice: ChatNotice) -> Self {
+        match notice {
+            ChatNotice::Message(message) => Self::ChatMessage(message),
+            ChatNotice::Receipt(receipt) => Self::ChatReceipt(receipt),
+            ChatNotice::Presence(update) => Self::Presence(update),
+        }
+    }
+}
+
+/// Outcome of a command
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct CommandResult {
+    pub version: u32,
+    /// Request id of the command; empty when the message could not be read
+    pub request_id: String,
+    pub ok: bool,
+    /// Call placed by a `dial`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub call_id: Option<String>,
+    /// Message sent by a `send_message`; receipts refer to it
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub message_id: Option<Uuid>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<CommandError>,
+}
+
+impl CommandResult {
+    pub fn success(request_id: String, call_id: Option<String>) -> Self {
+        Self {
+            version: WS_PROTOCOL_VERSION,
+            request_id,
+            ok: true,
+            call_id,
+            message_id: None,
+            error: None,
+        }
+    }
+
+    pub fn failure(request_id: String, error: CommandError) -> Self {
+        Self {
+            version: WS_PROTOCOL_VERSION,
+            request_id,
+            ok: false,
+            call_id: None,
+            message_id: None,
+            error: Some(error),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct CommandError {
+    pub code: CommandErrorCode,
+    pub message: String,
+}
+
+impl CommandError {
+    pub fn new(code: CommandErrorCode, message: impl Into<String>) -> Self {
+        Self {
+            code,
+            message: message.into(),
+        }
+    }
+}
+
+/// Why a command was not carried out
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CommandErrorCode {
+    /// Not a command message
+    InvalidRequest,
+    /// The client speaks another protocol version
+    UnsupportedVersion,
+    /// The socket was opened without credentials
+    Unauthenticated,
+    /// The user lacks the command's permission
+    Forbidden,
+    /// Too many commands; retry later
+    RateLimited,
+    /// The server has no service for the command
+    Unavailable,
+    /// The command was refused by the call or queue (e.g. unknown call)
+    Failed,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::application::chat::ReceiptStatus;
+    use serde_json::json;
+
+    fn round_trip<T>(value: &T)
+    where
+        T: Serialize + for<'de> Deserialize<'de> + PartialEq + std::fmt::Debug,
+    {
+        let json = serde_json::to_string(value).unwrap();
+        let parsed: T = serde_json::from_str(&json).unwrap();
+        assert_eq!(&parsed, value, "{}", json);
+    }
+
+    fn commands() -> Vec<Command> {
+        let call_id = "a84b4c76e66710".to_string();
+        vec![
+            Command::Dial {
+                to: "sip:bob@example.com".to_string(),
+            },
+            Command::Answer {
+                call_id: call_id.clone(),
+            },
+            Command::Hold {
+                call_id: call_id.clone(),
+            },
+            Command::Resume {
+                call_id: call_id.clone(),
+            },
+            Command::Transfer {
+                call_id: call_id.clone(),
+                target: "sip:carol@example.com".to_string(),
+            },
+            Command::Hangup { call_id },
+            Command::SetAgentState {
+                queue_id: Uuid::new_v4(),
+                member_id: Uuid::new_v4(),
+                state: AgentAvailability::Paused,
+            },
+            Command::GrantFloor {
+                room_id: Uuid::new_v4(),
+                participant_id: Uuid::new_v4(),
+            },
+            Command::RevokeFloor {
+                room_id: Uuid::new_v4(),
+                participant_id: Uuid::new_v4(),
+            },
+            Command::SendMessage {
+                to: "bob".to_string(),
+                text: "Can you take the next call?".to_string(),
+            },
+            Command::MarkRead {
+                message_id: Uuid::new_v4(),
+            },
+            Command::SetPresence {
+                state: ChatPresence::DoNotDisturb,
+            },
+            Command::SubscribePresence {
+                users: vec!["bob".to_string(), "carol".to_string()],
+            },
+        ]
+    }
+
+    #[test]
+    fn test_messages_round_trip() {
+        for (i, command) in commands().into_iter().enumerate() {
+            let request = CommandRequest {
+                version: WS_PROTOCOL_VERSION,
+                request_id: i.to_string(),
+                command,
+            };
+            round_trip(&request);
+        }
+
+        let codes = [
+            CommandErrorCode::InvalidRequest,
+            CommandErrorCode::UnsupportedVersion,
+            CommandErrorCode::Unauthenticated,
+            CommandErrorCode::Forbidden,
+            CommandErrorCode::RateLimited,
+            CommandErrorCode::Unavailable,
+            CommandErrorCode::Failed,
+        ];
+        for code in codes {
+            round_trip(&ControlMessage::CommandResult(CommandResult::failure(
+                "1".to_string(),
+                CommandError::new(code, "refused"),
+            )));
+        }
+        round_trip(&ControlMessage::CommandResult(CommandResult::success(
+            "2".to_string(),
+            Some("call-1".to_string()),
+        )));
+        round_trip(&ControlMessage::Hello {
+            version: WS_PROTOCOL_VERSION,
+            username: Some("alice".to_string()),
+        });
+        round_trip(&ControlMessage::Hello {
+            version: WS_PROTOCOL_VERSION,
+            username: None,
+        });
+        round_trip(&ControlMessage::CommandResult(CommandResult {
+            message_id: Some(Uuid::new_v4()),
+            ..CommandResult::success("3".to_string(), None)
+        }));
+        round_trip(&ControlMessage::ChatMessage(ChatMessage {
+            id: Uuid::new_v4(),
+            from: "alice".to_string(),
+            to: "bob".to_string(),
+            text: "hi".to_string(),
+            sent_at: chrono::Utc::now(),
+        }));
+        round_trip(&ControlMessage::ChatReceipt(ChatReceipt {
+            message_id: Uuid::new_v4(),
+            by: "bob".to_string(),
+            status: ReceiptStatus::Read,
+            at: chrono::Utc::now(),
+        }));
+        round_trip(&ControlMessage::Presence(PresenceUpdate {
+            username: "bob".to_string(),
+            state: ChatPresence::OnCall,
+        }));
+    }
+
+    #[test]
+    fn test_wire_format() {
+        let request: CommandRequest = serde_json::from_value(json!({
+            "version": 1,
+            "request_id": "7",
+            "command": "transfer",
+            "call_id": "call-1",
+            "target": "sip:carol@example.com",
+        }))
+        .unwrap();
+        assert_eq!(
+            request.command,
+            Command::Transfer {
+                call_id: "call-1".to_string(),
+                target: "sip:carol@example.com".to_string(),
+            }
+        );
+
+        let result = ControlMessage::CommandResult(CommandResult::failure(
+            "7".to_string(),
+            CommandError::new(CommandErrorCode::RateLimited, "Too many commands"),
+        ));
+        assert_eq!(
+            serde_json::to_value(&result).unwrap(),
+            json!({
+                "type": "CommandResult",
+                "data": {
+                    "version": 1,
+                    "request_id": "7",
+                    "ok": false,
+                    "error": {"code": "rate_limited", "message": "Too many commands"},
+                },
+            })
+        );
+    }
+}
@@ -3,11 +3,16 @@
 //! This is a Domain-Driven Design (DDD) implementation of a PBX system
 //! that supports SIP, WebRTC, and modern communication protocols.
 
+// Large `json!` schemas (e.g. the CDR's) nest deeper than the default
+#![recursion_limit = "256"]
+
 pub mod application;
 pub mod config;
 pub mod domain;
 pub mod infrastructure;
 pub mod interface;
+#[cfg(any(test, feature = "test-support"))]
+pub mod test_support;
 
 // Re-export commonly used types
 pub use domain::shared::error::DomainError;
@@ -1,36 +1,172 @@
-use yakyak::config::Config;
-use yakyak::domain::call::{Call, CallDirection, Participant};
+use yakyak::application::call::CallApplicationService;
+#[cfg(feature = "postgres")]
+use yakyak::application::chat::{spawn_agent_presence, spawn_call_presence, spawn_chat_retention, ChatService};
+use yakyak::application::events::EventBus;
+#[cfg(feature = "postgres")]
+use yakyak::application::privacy::AnonymizationService;
+#[cfg(feature = "postgres")]
+use yakyak::application::queue::AgentAvailabilityService;
+use yakyak::application::scheduled_call::{spawn_scheduled_call_runner, ScheduledCallService, WakeUpCallFeature};
+use yakyak::domain::scheduled_call::ScheduledCallRepository;
+use yakyak::config::{Config, Preflight, Redact};
+use yakyak::infrastructure::audit::logger::MemoryAuditBackend;
+use yakyak::infrastructure::audit::{spawn_call_audit, AuditLogger};
+use yakyak::domain::call::{Call, CallDirection, CallRepository, Participant};
+use yakyak::domain::audio::{AudioLibrary, AudioLibraryConfig};
+#[cfg(feature = "postgres")]
+use yakyak::domain::call_history::MissedCallTracker;
+use yakyak::domain::call_screening::ScreeningService;
+use yakyak::domain::call_survey::SurveyService;
+use yakyak::domain::dnd::DndManager;
+use yakyak::domain::feature_code::{features, standard_feature_code, FeatureCodeHandler, FeatureCodeRegistry};
+use yakyak::domain::instant_messaging::MessageRepository;
+use yakyak::domain::tenant_branding::BrandingRegistry;
+use yakyak::domain::routing::{AnnouncementRouteRepository, AnnouncementService, NoopRoutingLookup, RoutingLookup};
+#[cfg(feature = "postgres")]
+use yakyak::domain::routing::{AutoAttendantRepository, AutoAttendantService, RoutingDirectory};
 use yakyak::domain::shared::value_objects::{CallId, EndpointId, SessionId, SipUri};
 use yakyak::infrastructure::protocols::sip::{
-    AckHandler, ByeHandler, CancelHandler, InviteHandler, Registrar, SipMethod, SipServer,
-    SipServerConfig,
+    allow_header, AckHandler, AddressAdvertiser, ByeHandler, CancelHandler, CodecFallback, HeaderRulesEngine, HickoryLookup,
+    HoldSupervisor, HopTracker, InfoHandler, InternalServiceHandler, InviteHandler,
+    MaintenanceDrainer, MaintenanceRegistry, MessageHandler, OptionsHandler, OutboundRegistration, QuirksRegistry,
+    OverloadMonitor, OverloadSample, Registrar, SipMethod, SipResolver, SipServer, SipServerConfig,
+    SrtpRekeyer, SurveyRunner, SurvivabilityManager, TrunkManager, TrunkRegistrationMonitor,
 };
-use std::net::IpAddr;
+#[cfg(feature = "postgres")]
+use yakyak::infrastructure::protocols::sip::TransactionLayer;
+use yakyak::infrastructure::ivr::DtmfDispatcher;
+#[cfg(feature = "postgres")]
+use yakyak::infrastructure::ivr::UserIvrDirectory;
+use std::net::{IpAddr, SocketAddr};
 use std::sync::Arc;
-use tracing::{info, Level};
-use tracing_subscriber;
+use tracing::{info, warn};
+use tracing_subscriber::filter::LevelFilter;
+use tracing_subscriber::prelude::*;
+use yakyak::infrastructure::call_debug::CallDebugRegistry;
+use yakyak::infrastructure::lnp::{HttpRoutingLookup, LnpResolver};
+use yakyak::infrastructure::logging::LogRingBuffer;
+use yakyak::infrastructure::media::{BandwidthMonitor, CapacityMonitor, MohClassRegistry, RtpPortAllocator};
+use yakyak::infrastructure::replication::{ReplicationNode, ReplicationTls};
+use yakyak::infrastructure::secrets::SecretKeyring;
+use yakyak::infrastructure::sequence_vault::SequenceVault;
+use yakyak::infrastructure::setup_latency::SetupLatencyMonitor;
+use yakyak::infrastructure::storage::StorageGuard;
+use yakyak::infrastructure::telemetry::{self, spawn_call_span_recorder, CallSpans};
+#[cfg(feature = "postgres")]
+use yakyak::infrastructure::transcription::{DisabledTranscription, LiveTranscriptionService, TranscribingVoicemailRepository, VoicemailTranscriber};
+use yakyak::infrastructure::messaging::InProcessEventBus;
+#[cfg(not(feature = "postgres"))]
+use yakyak::infrastructure::persistence::{MemoryAnnouncementRouteRepository, MemoryCallRepository, MemoryMessageRepository, MemoryScheduledCallRepository};
 
 #[cfg(feature = "postgres")]
-use yakyak::infrastructure::persistence::{create_pool, run_migrations, DatabaseConfig, PgUserRepository, PgCdrRepository};
+use yakyak::infrastructure::persistence::{create_pool, run_migrations, DatabaseConfig, DbHealth, PgUserRepository, PgCdrRepository, PgSpeedDialRepository, PgVoicemailRepository, PgCallQueueRepository, PgQueueEventRepository, PgRoleRepository, PgMessageRepository, PgSipTrunkRepository, PgAnnouncementRouteRepository, PgAutoAttendantRepository, PgCallRepository, PgIdempotencyRepository, PgVoicemailListRepository, PgDeviceEnrollmentRepository, PgScheduledCallRepository, ResilientCdrRepository, CachingUserRepository, CdrJournal};
+#[cfg(feature = "postgres")]
+use yakyak::domain::privacy::SubjectStore;
+#[cfg(feature = "postgres")]
+use yakyak::infrastructure::idempotency::IdempotencyCache;
+#[cfg(feature = "postgres")]
+use yakyak::application::call::{spawn_cdr_retention, spawn_supervised_cdr_writer, CdrRetentionService};
+#[cfg(feature = "postgres")]
+use yakyak::infrastructure::messaging::PgOutboxEventBus;
+#[cfg(feature = "postgres")]
+use yakyak::domain::device_token::DeviceTokenStore;
+#[cfg(feature = "postgres")]
+use yakyak::domain::speed_dial::{SpeedDialRepository, SpeedDialService};
+use yakyak::domain::class_of_service::ClassOfServiceRegistry;
+#[cfg(feature = "postgres")]
+use yakyak::domain::fraud_detection::FraudDetector;
+#[cfg(feature = "postgres")]
+use yakyak::domain::sip_trunk::SipTrunkRepository;
+#[cfg(feature = "postgres")]
+use yakyak::domain::voicemail::VoicemailRepository;
 #[cfg(feature = "postgres")]
-use yakyak::infrastructure::protocols::sip::DigestAuthDb;
+use yakyak::domain::voicemail_list::{VoicemailListRepository, VoicemailListService};
+#[cfg(feature = "postgres")]
+use yakyak::domain::device_enrollment::{DeviceEnrollmentRepository, EnrollmentService};
+#[cfg(feature = "postgres")]
+use yakyak::domain::call_queue::CallQueueRepository;
+#[cfg(feature = "postgres")]
+use yakyak::domain::call_queue_engine::CallQueueEngine;
+#[cfg(feature = "postgres")]
+use yakyak::domain::conference_manager::{spawn_silence_release, ConferenceManager};
+#[cfg(feature = "postgres")]
+use yakyak::domain::queue_reporting::{spawn_queue_event_recorder, QueueEventRepository};
+#[cfg(feature = "postgres")]
+use yakyak::domain::security::SecurityAuditLogger;
+#[cfg(feature = "postgres")]
+use yakyak::domain::user::RoleRepository;
+#[cfg(feature = "postgres")]
+use yakyak::interface::api::diagnostics_handler::DiagnosticsContext;
+#[cfg(feature = "postgres")]
+use yakyak::infrastructure::persistence::health::{spawn_pool_monitor, spawn_recovery_listener};
+#[cfg(feature = "postgres")]
+use yakyak::infrastructure::protocols::sip::{DeviceResyncer, DigestAuthDb, Ha1Cache, MwiNotifier, MwiVoicemailRepository, WarmTransferManager};
 #[cfg(feature = "postgres")]
 use yakyak::interface::api::{build_router, init_metrics, update_active_calls, update_registered_users, AppState, EventBroadcaster};
+#[cfg(feature = "postgres")]
+use yakyak::interface::api::ws_handler::{forward_agent_state_changes, forward_bandwidth_warnings, forward_call_events, forward_capacity_events, forward_captions, forward_codec_fallbacks, forward_floor_events, forward_fraud_alerts, forward_maintenance_events, forward_registration_events, forward_scheduled_call_events, forward_slo_events, forward_storage_events, forward_survivability_events, forward_warm_transfers, publish_queue_wallboard};
 #[cfg(not(feature = "postgres"))]
 use yakyak::infrastructure::protocols::sip::DigestAuth;
 
 #[tokio::main]
 async fn main() -> anyhow::Result<()> {
+    let cli = parse_args()?;
+
+    // Load configuration: defaults, the file, then YAKYAK_ variables
+    let config = Config::load(cli.config_path.as_deref()).map_err(anyhow::Error::msg)?;
+
+    // `check-config` only reports configuration problems, all of them
+    if cli.check_config {
+        let report = Preflight::new(&config).run().await;
+        println!("{}", report);
+        std::process::exit(if report.can_start() { 0 } else { 1 });
+    }
+    config.validate().map_err(anyhow::Error::msg)?;
+
+    // Stored credentials are sealed with the configured secret key
+    let secret_keyring = SecretKeyring::from_config(&config.secrets)?.map(Arc::new);
+
+    // `rotate-secrets` reseals stored credentials with the current key
+    if cli.rotate_secrets {
+        return rotate_secrets(&config, secret_keyring).await;
+    }
+
     // Initialize tracing
-    tracing_subscriber::fmt()
-        .with_max_level(Level::INFO)
+    // Recent lines are also kept in memory for diagnostic bundles; calls with
+    // debug logging enabled are captured at every level, whatever the filter
+    let log_buffer = Arc::new(LogRingBuffer::default());
+    let call_debug = Arc::new(CallDebugRegistry::new(config.call_debug.clone()));
+    // Spans are exported over OTLP when tracing is enabled
+    let tracer_provider = if config.telemetry.enabled {
+        Some(telemetry::tracer_provider(&config.telemetry).map_err(anyhow::Error::msg)?)
+    } else {
+        None
+    };
+    tracing_subscriber::registry()
+        .with(tracer_provider.as_ref().map(|p| telemetry::layer(p, &config.telemetry.service_name)))
+        .with(tracing_subscriber::fmt::layer().with_filter(LevelFilter::INFO))
+        .with(log_buffer.layer().with_filter(LevelFilter::INFO))
+        .with(call_debug.layer())
         .init();
 
     info!("Starting YakYak PBX System");
+    // Logged redacted: log lines end up in diagnostic bundles
+    info!("Configuration loaded: {}", config.redacted());
 
-    // Load configuration
-    let config = Config::default();
-    info!("Configuration loaded: {:?}", config);
+    // Report every configuration problem; fatal ones stop the startup
+    let config_report = Arc::new(Preflight::new(&config).run().await);
+    for finding in config_report.fatal() {
+        tracing::error!("Configuration: {}", finding);
+    }
+    for finding in config_report.warnings() {
+        tracing::warn!("Configuration: {}", finding);
+    }
+    if !config_report.can_start() {
+        anyhow::bail!(
+            "Configuration has {} fatal problems",
+            config_report.fatal().count()
+        );
+    }
 
     // Demo: Create a sample call to verify domain model
     demo_call_lifecycle().await?;
@@ -39,7 +175,7 @@ async fn main() -> anyhow::Result<()> {
 
     // Initialize database and API server (if postgres feature is enabled)
     #[cfg(feature = "postgres")]
-    let (user_repository, cdr_repository): (Arc<dyn yakyak::domain::user::UserRepository>, Option<Arc<dyn yakyak::domain::cdr::CdrRepository>>) = {
+    let (user_repository, role_repository, cdr_repository, speed_dial_repository, voicemail_repository, call_queue_repository, queue_event_repository, message_repository, sip_trunk_repository, announcement_route_repository, auto_attendant_repository, voicemail_list_repository, device_enrollment_repository, scheduled_call_repository, db_health, call_event_bus, cdr_retention, resilient_cdr_repository, call_repository, privacy_stores, idempotency): (Arc<dyn yakyak::domain::user::UserRepository>, Arc<dyn RoleRepository>, Option<Arc<dyn yakyak::domain::cdr::CdrRepository>>, Arc<dyn SpeedDialRepository>, Arc<dyn VoicemailRepository>, Arc<dyn CallQueueRepository>, Arc<dyn QueueEventRepository>, Arc<dyn MessageRepository>, Arc<dyn SipTrunkRepository>, Arc<dyn AnnouncementRouteRepository>, Arc<dyn AutoAttendantRepository>, Arc<dyn VoicemailListRepository>, Arc<dyn DeviceEnrollmentRepository>, Arc<dyn ScheduledCallRepository>, Arc<DbHealth>, Arc<dyn EventBus>, Arc<CdrRetentionService>, Arc<ResilientCdrRepository>, Arc<dyn CallRepository>, Vec<Arc<dyn SubjectStore>>, Option<Arc<IdempotencyCache>>) = {
         info!("Initializing database connection...");
 
         // Create database pool
@@ -60,39 +196,262 @@ async fn main() -> anyhow::Result<()> {
         run_migrations(&pool).await?;
         info!("Database migrations completed");
 
+        // Watch the pool so a failover switches the server into degraded mode
+        let db_health = Arc::new(DbHealth::default());
+        spawn_pool_monitor(pool.clone(), db_health.clone());
+        info!("Database health monitor started");
+
         // Create user repository
         let user_repo: Arc<dyn yakyak::domain::user::UserRepository> = Arc::new(PgUserRepository::new(pool.clone()));
         info!("User repository initialized");
 
+        // Create role repository
+        let role_repo: Arc<dyn RoleRepository> = Arc::new(PgRoleRepository::new(pool.clone()));
+        info!("Role repository initialized");
+
         // Create CDR repository
-        let cdr_repo: Arc<dyn yakyak::domain::cdr::CdrRepository> = Arc::new(PgCdrRepository::new(pool.clone()));
+        // CDR writes are queued while degraded and flushed on recovery; the
+        // queue is journaled to disk so a restart does not lose it
+        let cdr_journal = if config.database.cdr_journal_path.is_empty() {
+            CdrJournal::in_memory()
+        } else {
+            CdrJournal::open(&config.database.cdr_journal_path).unwrap_or_else(|e| {
+                warn!(
+                    "Failed to open CDR journal {}: {}, queueing in memory only",
+                    config.database.cdr_journal_path, e
+                );
+                CdrJournal::in_memory()
+            })
+        };
+        let resilient_cdr_repo = Arc::new(
+            ResilientCdrRepository::new(
+                Arc::new(PgCdrRepository::new(pool.clone())),
+                db_health.clone(),
+            )
+            .with_journal(cdr_journal),
+        );
+        {
+            // Writes recovered from the journal
+            let repo = resilient_cdr_repo.clone();
+            tokio::spawn(async move { repo.flush().await });
+
+            let repo = resilient_cdr_repo.clone();
+            spawn_recovery_listener(db_health.clone(), move || {
+                let repo = repo.clone();
+                async move {
+                    repo.flush().await;
+                }
+            });
+        }
+        let cdr_repo: Arc<dyn yakyak::domain::cdr::CdrRepository> = resilient_cdr_repo.clone();
         info!("CDR repository initialized");
 
-        (user_repo, Some(cdr_repo))
+        // CDR retention runs on its schedule and on demand from the admin API
+        let cdr_retention = Arc::new(CdrRetentionService::new(
+            config.cdr_retention.clone(),
+            Arc::new(PgCdrRepository::new(pool.clone())),
+        ));
+        if config.cdr_retention.enabled {
+            spawn_cdr_retention(cdr_retention.clone());
+            info!("CDR retention scheduled every {}s", config.cdr_retention.interval_secs);
+        }
+
+        // Create speed dial repository
+        let speed_dial_repo: Arc<dyn SpeedDialRepository> = Arc::new(PgSpeedDialRepository::new(pool.clone()));
+        info!("Speed dial repository initialized");
+
+        // Create voicemail repository
+        let voicemail_repo: Arc<dyn VoicemailRepository> = Arc::new(PgVoicemailRepository::new(pool.clone()));
+        info!("Voicemail repository initialized");
+
+        // Deposited messages are transcribed and announced in the background
+        let transcription = config.transcription.provider().unwrap_or_else(|e| {
+            tracing::warn!("Voicemail transcription unavailable: {}", e);
+            Arc::new(DisabledTranscription)
+        });
+        let transcriber = VoicemailTranscriber::new(transcription, voicemail_repo.clone(), config.transcription.clone())
+            .with_user_repository(user_repo.clone());
+        let voicemail_repo: Arc<dyn VoicemailRepository> =
+            Arc::new(TranscribingVoicemailRepository::new(voicemail_repo, Arc::new(transcriber)));
+
+        // Create call queue and queue event repositories
+        let call_queue_repo: Arc<dyn CallQueueRepository> = Arc::new(PgCallQueueRepository::new(pool.clone()));
+        let queue_event_repo: Arc<dyn QueueEventRepository> = Arc::new(PgQueueEventRepository::new(pool.clone()));
+        info!("Call queue repositories initialized");
+
+        // Create instant message repository
+        let message_repo: Arc<dyn MessageRepository> = Arc::new(PgMessageRepository::new(pool.clone()));
+        info!("Message repository initialized");
+
+        // Create SIP trunk repository
+        let mut sip_trunk_repo = PgSipTrunkRepository::new(pool.clone());
+        if let Some(keyring) = &secret_keyring {
+            sip_trunk_repo = sip_trunk_repo.with_keyring(keyring.clone());
+        }
+        let sip_trunk_repo: Arc<dyn SipTrunkRepository> = Arc::new(sip_trunk_repo);
+        info!("SIP trunk repository initialized");
+
+        let announcement_route_repo: Arc<dyn AnnouncementRouteRepository> =
+            Arc::new(PgAnnouncementRouteRepository::new(pool.clone()));
+
+        let auto_attendant_repo: Arc<dyn AutoAttendantRepository> =
+            Arc::new(PgAutoAttendantRepository::new(pool.clone()));
+
+        let voicemail_list_repo: Arc<dyn VoicemailListRepository> =
+            Arc::new(PgVoicemailListRepository::new(pool.clone()));
+
+        // Softphone enrollment tokens and device credentials
+        let device_enrollment_repo: Arc<dyn DeviceEnrollmentRepository> =
+            Arc::new(PgDeviceEnrollmentRepository::new(pool.clone()));
+
+        // Wake-up calls and reminders
+        let scheduled_call_repo: Arc<dyn ScheduledCallRepository> =
+            Arc::new(PgScheduledCallRepository::new(pool.clone()));
+
+        // Call events go through the outbox when at-least-once delivery is wanted
+        let call_event_bus: Arc<dyn EventBus> = if config.database.event_outbox {
+            let outbox = Arc::new(PgOutboxEventBus::new(pool.clone()));
+            outbox.clone().spawn_relay(std::time::Duration::from_secs(5));
+            info!("Call events delivered through the event outbox");
+            outbox
+        } else {
+            Arc::new(InProcessEventBus::default())
+        };
+
+        // Call aggregates are saved so another node or a restart can take calls over
+        let call_repo: Arc<dyn CallRepository> = Arc::new(PgCallRepository::new(pool.clone()));
+
+        // Stores erasure requests find a subject's personal data in
+        let mut voicemail_store = PgVoicemailRepository::new(pool.clone());
+        if let Some(dir) = config.storage.voicemail.dir.clone() {
+            voicemail_store = voicemail_store.with_audio_dir(dir);
+        }
+        let privacy_stores: Vec<Arc<dyn SubjectStore>> = vec![
+            Arc::new(PgCdrRepository::new(pool.clone())),
+            Arc::new(PgMessageRepository::new(pool.clone())),
+            Arc::new(voicemail_store),
+        ];
+
+        // Responses to requests with an Idempotency-Key outlive a restart
+        let idempotency = config.server.idempotency.enabled.then(|| {
+            let cache = Arc::new(
+                IdempotencyCache::new(config.server.idempotency.clone())
+                    .with_repository(Arc::new(PgIdempotencyRepository::new(pool.clone()))),
+            );
+            cache.clone().spawn_purge(std::time::Duration::from_secs(3600));
+            cache
+        });
+
+        (user_repo, role_repo, Some(cdr_repo), speed_dial_repo, voicemail_repo, call_queue_repo, queue_event_repo, message_repo, sip_trunk_repo, announcement_route_repo, auto_attendant_repo, voicemail_list_repo, device_enrollment_repo, scheduled_call_repo, db_health, call_event_bus, cdr_retention, resilient_cdr_repo, call_repo, privacy_stores, idempotency)
     };
 
     #[cfg(not(feature = "postgres"))]
     let user_repository: Option<Arc<dyn yakyak::domain::user::UserRepository>> = None;
     #[cfg(not(feature = "postgres"))]
     let cdr_repository: Option<Arc<dyn yakyak::domain::cdr::CdrRepository>> = None;
+    #[cfg(not(feature = "postgres"))]
+    let call_event_bus: Arc<dyn EventBus> = Arc::new(InProcessEventBus::default());
+    #[cfg(not(feature = "postgres"))]
+    let message_repository: Arc<dyn MessageRepository> = Arc::new(MemoryMessageRepository::new());
+    #[cfg(not(feature = "postgres"))]
+    let announcement_route_repository: Arc<dyn AnnouncementRouteRepository> =
+        Arc::new(MemoryAnnouncementRouteRepository::new());
+    #[cfg(not(feature = "postgres"))]
+    let call_repository: Arc<dyn CallRepository> = Arc::new(MemoryCallRepository::new());
+    #[cfg(not(feature = "postgres"))]
+    let scheduled_call_repository: Arc<dyn ScheduledCallRepository> =
+        Arc::new(MemoryScheduledCallRepository::new());
+
+    // Call aggregates publish lifecycle events; the CDR writer applies answers and hangups
+    let call_events = Arc::new(
+        CallApplicationService::new(call_event_bus.clone()).with_repository(call_repository.clone()),
+    );
+    #[cfg(feature = "postgres")]
+    if let Some(ref cdr_repo) = cdr_repository {
+        spawn_supervised_cdr_writer(
+            call_event_bus.as_ref(),
+            cdr_repo.clone(),
+            config.answer_supervision.clone(),
+        );
+    }
+    let audit_backend = Arc::new(MemoryAuditBackend::new(10_000));
+    let audit_logger = Arc::new(AuditLogger::new(audit_backend.clone()));
+    spawn_call_audit(call_event_bus.as_ref(), audit_logger.clone());
 
     // Start SIP server
+    let sip_bind = SocketAddr::new(
+        config.sip.bind_address.parse().expect("Invalid SIP bind address"),
+        config.sip.bind_port,
+    );
+    let sip_bind_v6 = config.sip.bind_address_v6.as_ref().map(|address| {
+        SocketAddr::new(
+            address.parse().expect("Invalid SIP IPv6 bind address"),
+            config.sip.bind_port,
+        )
+    });
     let sip_config = SipServerConfig {
-        udp_bind: format!("{}:{}", config.sip.bind_address, config.sip.bind_port)
-            .parse()
-            .unwrap(),
-        tcp_bind: format!("{}:{}", config.sip.bind_address, config.sip.bind_port)
-            .parse()
-            .unwrap(),
+        udp_bind: sip_bind,
+        tcp_bind: sip_bind,
+        udp6_bind: sip_bind_v6,
+        tcp6_bind: sip_bind_v6,
         domain: config.sip.domain.clone(),
         enable_tcp: true,
+        tcp_idle_timeout_secs: config.sip.tcp_idle_timeout_secs,
+        tcp_max_connections: config.sip.tcp_max_connections,
+        timers: config.sip.timers.clone(),
+        ..Default::default()
     };
 
-    let mut sip_server = SipServer::new(sip_config);
+    let quirks = Arc::new(QuirksRegistry::new(config.sip.quirks.clone()).map_err(anyhow::Error::msg)?);
+    info!("Loaded {} interop quirk rules", quirks.rules().count());
+    // Our Via on forwarded requests; requests coming back with it have looped
+    let hop_tracker = Arc::new(HopTracker::new(format!(
+        "{}:{}",
+        config.sip.domain, config.sip.bind_port
+    )));
+    let header_rules = Arc::new(
+        HeaderRulesEngine::new(config.sip.header_rules.clone())
+            .map_err(|e| anyhow::anyhow!("Invalid header rules: {}", e))?,
+    );
+    // Sheds optional work, then refuses new dialogs with 503 under overload
+    let overload = Arc::new(OverloadMonitor::new(config.sip.overload.clone()));
+    let mut sip_server = SipServer::new(sip_config)
+        .with_quirks(quirks.clone())
+        .with_hop_tracker(hop_tracker.clone())
+        .with_header_rules(header_rules.clone())
+        .with_overload_monitor(overload.clone());
+
+    // Tenants and trunks taken out of service through the API
+    let maintenance = Arc::new(MaintenanceRegistry::new().with_header_rules(header_rules.clone()));
+
+    // Local registration and routing of a branch node cut off from its upstream
+    let survivability = config
+        .sip
+        .survivability
+        .enabled
+        .then(|| Arc::new(SurvivabilityManager::new(config.sip.survivability.clone())));
+
+    // Channels per trunk, reserved for inbound and outbound calls
+    let trunk_channels = (!config.sip.trunk_channels.trunks.is_empty())
+        .then(|| Arc::new(TrunkManager::new(config.sip.trunk_channels.clone())));
 
     // Initialize authentication
     #[cfg(feature = "postgres")]
-    let auth = Arc::new(DigestAuthDb::new(config.sip.domain.clone(), user_repository.clone()));
+    let auth = {
+        let auth = DigestAuthDb::new(config.sip.domain.clone(), user_repository.clone())
+            .with_db_health(db_health.clone())
+            .with_device_credentials(device_enrollment_repository.clone());
+
+        if config.database.degraded_auth_cache {
+            info!("Degraded-mode HA1 cache enabled");
+            Arc::new(auth.with_ha1_cache(Arc::new(Ha1Cache::new(
+                std::time::Duration::from_secs(config.database.degraded_auth_cache_ttl_secs),
+                config.database.degraded_auth_cache_capacity,
+            ))))
+        } else {
+            Arc::new(auth)
+        }
+    };
 
     #[cfg(not(feature = "postgres"))]
     let auth = {
@@ -105,7 +464,11 @@ async fn main() -> anyhow::Result<()> {
     };
 
     // Register SIP handlers with authentication
-    let registrar = Arc::new(Registrar::with_auth(auth.clone()));
+    let mut registrar = Registrar::with_auth(auth.clone()).with_maintenance(maintenance.clone());
+    if let Some(survivability) = &survivability {
+        registrar = registrar.with_survivability(survivability.clone());
+    }
+    let registrar = Arc::new(registrar);
     sip_server
         .register_handler(SipMethod::Register, registrar.clone())
         .await;
@@ -113,13 +476,343 @@ async fn main() -> anyhow::Result<()> {
     // Register call handlers with authentication
     let local_ip: IpAddr = "0.0.0.0".parse().unwrap(); // Use actual local IP in production
 
+    // External address for SDP/Contact/Via when running behind NAT
+    let external = &config.sip.external_address;
+    let address_advertiser = Arc::new(
+        AddressAdvertiser::from_config(external).map_err(anyhow::Error::msg)?,
+    );
+    if external.advertised_address.is_none() {
+        if let Some(stun_server) = &external.stun_server {
+            match tokio::net::lookup_host(stun_server.as_str()).await.ok().and_then(|mut a| a.next()) {
+                Some(server) => {
+                    address_advertiser.clone().spawn_stun_discovery(
+                        server,
+                        std::time::Duration::from_secs(external.stun_recheck_secs),
+                    );
+                    info!("Discovering external address via STUN server {}", server);
+                }
+                None => tracing::warn!("Cannot resolve STUN server {}", stun_server),
+            }
+        }
+    }
+
+    // NAPTR/SRV resolution of registrars and forwarded calls
+    let sip_resolver = if config.sip.dns.enabled {
+        match HickoryLookup::from_system_conf() {
+            Ok(lookup) => Some(Arc::new(SipResolver::new(
+                config.sip.dns.clone(),
+                Arc::new(lookup),
+            ))),
+            Err(e) => {
+                warn!("{}; resolving SIP hosts as plain names", e);
+                None
+            }
+        }
+    } else {
+        None
+    };
+
+    // Trunks whose providers want the PBX to register
+    let mut outbound_registration = OutboundRegistration::new(
+        sip_server.outbound_sender(),
+        format!("{}:{}", config.sip.domain, config.sip.bind_port),
+    )
+    .with_policy(config.sip.outbound_registration.clone())
+    .with_timer_config(config.sip.timers.clone())
+    .with_address_advertiser(address_advertiser.clone());
+    if let Some(resolver) = &sip_resolver {
+        outbound_registration = outbound_registration.with_resolver(resolver.clone());
+    }
+    #[cfg(feature = "postgres")]
+    let outbound_registration = outbound_registration.with_repository(sip_trunk_repository.clone());
+    let outbound_registration = Arc::new(outbound_registration);
+    #[cfg(feature = "postgres")]
+    match sip_trunk_repository.list_trunks(true).await {
+        Ok(trunks) => {
+            let registering = trunks
+                .into_iter()
+                .filter(|trunk| outbound_registration.add_trunk(trunk.clone()))
+                .count();
+            info!("{} trunks register upstream", registering);
+        }
+        Err(e) => tracing::warn!("Failed to load SIP trunks: {}", e),
+    }
+    outbound_registration
+        .clone()
+        .spawn_response_listener(sip_server.subscribe_responses());
+    outbound_registration
+        .clone()
+        .spawn(std::time::Duration::from_secs(1));
+    if let Some(survivability) = &survivability {
+        let monitor = TrunkRegistrationMonitor::new(
+            outbound_registration.clone(),
+            &config.sip.survivability.upstream_trunk,
+        );
+        survivability.clone().spawn(Arc::new(monitor));
+        info!(
+            "Survivability enabled: watching upstream trunk {}",
+            config.sip.survivability.upstream_trunk
+        );
+    }
+
+    // Security audit trail shared by fraud detection and admin endpoints
+    #[cfg(feature = "postgres")]
+    let security_audit_logger = Arc::new(SecurityAuditLogger::new(10_000));
+
+    // Toll fraud detection for outbound calls
+    #[cfg(feature = "postgres")]
+    let fraud_detector = {
+        let detector = Arc::new(
+            FraudDetector::new(config.fraud.rules.clone())
+                .with_audit_logger(security_audit_logger.clone()),
+        );
+        for (realm, rules) in &config.fraud.tenants {
+            detector.set_tenant_rules(realm, rules.clone());
+        }
+        let state_path = std::path::PathBuf::from(&config.fraud.state_path);
+        if state_path.exists() {
+            if let Err(e) = detector.load_from_file(&state_path) {
+                tracing::warn!("Failed to load fraud counters from {:?}: {}", state_path, e);
+            }
+        }
+        detector.clone().spawn_persistence(
+            state_path,
+            std::time::Duration::from_secs(config.fraud.persist_interval_secs),
+        );
+        detector
+    };
+
+    let audio_library = Arc::new(AudioLibrary::new(AudioLibraryConfig {
+        root: config.audio.library_root.clone().into(),
+        auto_convert: config.audio.auto_convert,
+        pcm_cache_capacity: config.audio.pcm_cache_capacity,
+    }));
+
+    // Tenant MOH, prompts and outbound caller identity
+    let branding = Arc::new(
+        BrandingRegistry::new(config.branding.default_moh_source.clone())
+            .with_audio_library(audio_library.clone()),
+    );
+    for (realm, tenant) in &config.branding.tenants {
+        branding.set_tenant_branding(realm, tenant.clone());
+    }
+
+    // MOH playlists selected per queue or tenant
+    let (moh_classes, moh_errors) = MohClassRegistry::from_config(&config.media.moh, audio_library.clone());
+    for e in moh_errors {
+        warn!("{}", e);
+    }
+    let moh_classes = Arc::new(moh_classes);
+
+    // Pre-answer screening of calls to users who enable it
+    let numbering = Arc::new(config.numbering.clone());
+    let screening = Arc::new(
+        ScreeningService::new(config.screening.clone()).with_numbering_plan(numbering.clone()),
+    );
+
+    // Post-call surveys of queue callers
+    let surveys = Arc::new(SurveyService::new(config.surveys.clone()));
+
+    // Our own domain is always an internal redirect target
+    let mut redirect_policy = config.sip.redirect.clone();
+    if !redirect_policy.internal_domains.contains(&config.sip.domain) {
+        redirect_policy.internal_domains.push(config.sip.domain.clone());
+    }
+
+    // Wake-up calls and reminders, set over the API or with *77.
+    // No outbound INVITE transport to place scheduled calls with yet
+    let scheduled_calls = Arc::new(
+        ScheduledCallService::new(scheduled_call_repository.clone(), config.scheduled_calls.clone())
+            .with_time_zones(config.time_zones.clone()),
+    );
+    spawn_scheduled_call_runner(scheduled_calls.clone());
+
+    // Star codes of PBX features; codes that could be confused are refused
+    let dnd = Arc::new(DndManager::new());
+    let wake_up_call = standard_feature_code(features::WAKE_UP_CALL)
+        .expect("wake-up call is a standard feature code");
+    let wake_up_handler: Arc<dyn FeatureCodeHandler> =
+        Arc::new(WakeUpCallFeature::new(scheduled_calls.clone()));
+    let feature_codes = Arc::new(
+        FeatureCodeRegistry::standard_with(
+            config.feature_codes.clone(),
+            dnd.clone(),
+            vec![(wake_up_call, wake_up_handler)],
+        )
+        .map_err(|e| anyhow::anyhow!("Invalid feature codes: {}", e))?,
+    );
+
+    // Load feedback steering new calls to low-bandwidth codecs
+    let capacity_monitor = Arc::new(CapacityMonitor::new(config.media.capacity.clone()));
+
+    // Bandwidth estimates and REMB feedback of WebRTC legs
+    let bandwidth_monitor = config
+        .media
+        .bandwidth
+        .enabled
+        .then(|| Arc::new(BandwidthMonitor::new(config.media.bandwidth.clone())));
+
+    // Disk quotas and free-space floor, checked before recordings and messages
+    let storage_guard = Arc::new(StorageGuard::new(config.storage.clone()));
+    storage_guard.clone().spawn_monitor();
+
+    // Call setup times, checked against the latency SLOs
+    let setup_latency = Arc::new(SetupLatencyMonitor::new(config.setup_latency.clone()));
+    if !config.setup_latency.slos.is_empty() {
+        setup_latency.clone().spawn_evaluator();
+    }
+
+    // Number portability lookups of outbound calls, cached per number
+    let routing_lookup: Arc<dyn RoutingLookup> = if config.lnp.enabled {
+        Arc::new(
+            HttpRoutingLookup::new(&config.lnp)
+                .map_err(|e| anyhow::anyhow!("Invalid LNP configuration: {}", e))?,
+        )
+    } else {
+        Arc::new(NoopRoutingLookup)
+    };
+    let lnp = Arc::new(LnpResolver::new(
+        &config.lnp,
+        routing_lookup,
+        config.numbering.clone(),
+    ));
+
+    // Announcement-only numbers, e.g. opening hours lines
+    let announcements = Arc::new(
+        AnnouncementService::new(announcement_route_repository.clone())
+            .with_audio_library(audio_library.clone())
+            .with_time_zones(config.time_zones.clone())
+            .with_voicemail_uri(config.feature_codes.voicemail_uri.clone()),
+    );
+
+    // Per-call DTMF stream (RTP telephone-events and INFO)
+    let dtmf_dispatcher = Arc::new(DtmfDispatcher::new());
+
+    // Reception auto-attendants with extension dialing and dial-by-name
+    #[cfg(feature = "postgres")]
+    let auto_attendants = Arc::new(
+        AutoAttendantService::new(auto_attendant_repository.clone())
+            .with_time_zones(config.time_zones.clone())
+            .with_voicemail_uri(config.feature_codes.voicemail_uri.clone()),
+    );
+
+    // Test services installers dial to check the audio path
+    let internal_services = Arc::new(InternalServiceHandler::new(
+        config.sip.internal_services.clone(),
+    ));
+
+    // Dialog CSeqs and media SSRCs continue where the previous run left off
+    let sequence_vault = if config.sequences.enabled {
+        let vault = match SequenceVault::open(&config.sequences) {
+            Ok(vault) => vault,
+            Err(e) => {
+                tracing::warn!(
+                    "Failed to recover sequence state from {}: {}",
+                    config.sequences.state_path, e
+                );
+                SequenceVault::new(config.sequences.cseq_margin)
+                    .with_path(&config.sequences.state_path)
+            }
+        };
+        let vault = Arc::new(vault);
+        vault.clone().spawn_flusher(std::time::Duration::from_millis(
+            config.sequences.flush_interval_ms,
+        ));
+        Some(vault)
+    } else {
+        None
+    };
+
+    // Destination categories users may call, kept across restarts
+    let class_of_service = Arc::new(ClassOfServiceRegistry::from_config(&config.class_of_service));
+    if config.class_of_service.enabled {
+        let state_path = std::path::PathBuf::from(&config.class_of_service.state_path);
+        if state_path.exists() {
+            if let Err(e) = class_of_service.load_from_file(&state_path) {
+                tracing::warn!("Failed to load classes of service from {:?}: {}", state_path, e);
+            }
+        }
+        class_of_service.clone().spawn_persistence(
+            state_path,
+            std::time::Duration::from_secs(config.class_of_service.persist_interval_secs),
+        );
+    }
+
+    // RTP port pairs of calls, returned when the call ends
+    let rtp_ports = Arc::new(RtpPortAllocator::new(config.sip.rtp_port_min, config.sip.rtp_port_max));
+    info!(
+        "RTP ports {}-{} ({} calls)",
+        config.sip.rtp_port_min,
+        config.sip.rtp_port_max,
+        rtp_ports.capacity()
+    );
+
     #[cfg(feature = "postgres")]
     let invite_handler = {
-        let handler = InviteHandler::with_auth(
+        let mut handler = InviteHandler::with_auth(
             registrar.clone(),
             local_ip,
             auth.clone(),
-        );
+        )
+        .with_port_allocator(rtp_ports.clone())
+        .with_redirect_policy(redirect_policy.clone())
+        .with_transfer_policy(config.sip.transfer.clone())
+        .with_takeover_policy(config.sip.takeover.clone())
+        .with_ringback(config.media.ringback.clone())
+        .with_internal_services(internal_services.clone())
+        .with_address_advertiser(address_advertiser.clone())
+        .with_call_events(call_events.clone())
+        .with_hop_tracker(hop_tracker.clone())
+        .with_branding(branding.clone())
+        .with_moh_classes(moh_classes.clone())
+        .with_header_rules(header_rules.clone())
+        .with_maintenance(maintenance.clone())
+        .with_call_debug(call_debug.clone())
+        .with_call_screening(screening.clone())
+        .with_ptime_bounds(config.media.min_ptime_ms, config.media.max_ptime_ms)
+        .with_audio_level_extension(config.media.audio_level_extension)
+        .with_capacity_monitor(capacity_monitor.clone())
+        .with_setup_latency(setup_latency.clone())
+        .with_feature_codes(feature_codes.clone())
+        .with_announcements(announcements.clone())
+        .with_auto_attendants(
+            auto_attendants.clone(),
+            Arc::new(
+                UserIvrDirectory::new(user_repository.clone(), config.sip.domain.clone())
+                    .with_voicemail(voicemail_repository.clone()),
+            ),
+            dtmf_dispatcher.clone(),
+        )
+        .with_lnp(lnp.clone())
+        .with_overload_monitor(overload.clone())
+        .with_speed_dials(Arc::new(
+            SpeedDialService::new(speed_dial_repository.clone(), user_repository.clone())
+                .with_numbering_plan(numbering.clone()),
+        ));
+        if let Some(bind_v6) = sip_bind_v6 {
+            handler = handler.with_local_ipv6(bind_v6.ip());
+        }
+        if let Some(resolver) = &sip_resolver {
+            handler = handler.with_sip_resolver(resolver.clone());
+        }
+        if let Some(vault) = &sequence_vault {
+            handler = handler.with_sequence_vault(vault.clone());
+        }
+        if let Some(monitor) = &bandwidth_monitor {
+            handler = handler.with_bandwidth_monitor(monitor.clone());
+        }
+        if config.class_of_service.enabled {
+            handler = handler.with_class_of_service(class_of_service.clone(), dtmf_dispatcher.clone());
+        }
+        if let Some(survivability) = &survivability {
+            handler = handler.with_survivability(survivability.clone());
+        }
+        if let Some(trunks) = &trunk_channels {
+            handler = handler.with_trunk_manager(trunks.clone());
+        }
+        if config.fraud.enabled {
+            handler = handler.with_fraud_detection(fraud_detector.clone());
+        }
 
         // Add CDR repository if available
         if let Some(ref cdr_repo) = cdr_repository {
@@ -130,15 +823,140 @@ async fn main() -> anyhow::Result<()> {
     };
 
     #[cfg(not(feature = "postgres"))]
-    let invite_handler = Arc::new(InviteHandler::with_auth(
-        registrar.clone(),
-        local_ip,
-        auth.clone(),
-    ));
+    let invite_handler = {
+        let mut handler = InviteHandler::with_auth(registrar.clone(), local_ip, auth.clone())
+            .with_port_allocator(rtp_ports.clone())
+            .with_redirect_policy(redirect_policy.clone())
+            .with_transfer_policy(config.sip.transfer.clone())
+            .with_takeover_policy(config.sip.takeover.clone())
+            .with_ringback(config.media.ringback.clone())
+            .with_internal_services(internal_services.clone())
+            .with_address_advertiser(address_advertiser.clone())
+            .with_call_events(call_events.clone())
+            .with_hop_tracker(hop_tracker.clone())
+            .with_branding(branding.clone())
+            .with_moh_classes(moh_classes.clone())
+            .with_header_rules(header_rules.clone())
+            .with_maintenance(maintenance.clone())
+            .with_call_debug(call_debug.clone())
+            .with_call_screening(screening.clone())
+            .with_ptime_bounds(config.media.min_ptime_ms, config.media.max_ptime_ms)
+            .with_audio_level_extension(config.media.audio_level_extension)
+            .with_capacity_monitor(capacity_monitor.clone())
+            .with_setup_latency(setup_latency.clone())
+            .with_feature_codes(feature_codes.clone())
+            .with_announcements(announcements.clone())
+            .with_lnp(lnp.clone())
+            .with_overload_monitor(overload.clone());
+        if let Some(bind_v6) = sip_bind_v6 {
+            handler = handler.with_local_ipv6(bind_v6.ip());
+        }
+        if let Some(resolver) = &sip_resolver {
+            handler = handler.with_sip_resolver(resolver.clone());
+        }
+        if let Some(vault) = &sequence_vault {
+            handler = handler.with_sequence_vault(vault.clone());
+        }
+        if let Some(monitor) = &bandwidth_monitor {
+            handler = handler.with_bandwidth_monitor(monitor.clone());
+        }
+        if config.class_of_service.enabled {
+            handler = handler.with_class_of_service(class_of_service.clone(), dtmf_dispatcher.clone());
+        }
+        if let Some(survivability) = &survivability {
+            handler = handler.with_survivability(survivability.clone());
+        }
+        if let Some(trunks) = &trunk_channels {
+            handler = handler.with_trunk_manager(trunks.clone());
+        }
+        Arc::new(handler)
+    };
 
     let active_calls = invite_handler.active_calls.clone();
     let call_router = invite_handler.call_router();
 
+    // Cross-checks of the dial plan and routing configuration; problems are
+    // logged, `check-routing` prints them and exits
+    let routing_linter = {
+        let linter = invite_handler
+            .routing_linter()
+            .with_source(audio_library.clone())
+            .with_source(surveys.clone());
+        #[cfg(feature = "postgres")]
+        let linter = linter.with_source(Arc::new(
+            RoutingDirectory::new()
+                .with_users(user_repository.clone())
+                .with_trunks(sip_trunk_repository.clone())
+                .with_queues(call_queue_repository.clone()),
+        ));
+        Arc::new(linter)
+    };
+    let routing_lint = routing_linter.lint().await;
+    if cli.check_routing {
+        println!("{}", routing_lint);
+        std::process::exit(if routing_lint.has_errors() { 1 } else { 0 });
+    }
+    for finding in &routing_lint.findings {
+        warn!("Routing lint: {}", finding);
+    }
+
+    // Load of the SIP ingress path, sampled for overload control
+    if config.sip.overload.enabled {
+        #[cfg(feature = "postgres")]
+        let cdr_queue = {
+            let repo = resilient_cdr_repository.clone();
+            move || repo.pending()
+        };
+        #[cfg(not(feature = "postgres"))]
+        let cdr_queue = || 0;
+        let transactions = sip_server.transaction_counter();
+        let ports = invite_handler.port_allocator();
+        overload.clone().spawn_sampler(move || OverloadSample {
+            transactions: transactions(),
+            port_usage: ports.in_use() as f64 / ports.capacity().max(1) as f64,
+            cdr_queue: cdr_queue(),
+            ..Default::default()
+        });
+        info!("SIP overload control enabled");
+    }
+
+    // End the calls of maintained scopes at their drain deadlines
+    Arc::new(
+        MaintenanceDrainer::new(maintenance.clone(), call_router.clone()).with_outbound(
+            sip_server.outbound_sender(),
+            format!("{}:{}", config.sip.domain, config.sip.bind_port),
+        ),
+    )
+    .spawn();
+
+    // One trace per call: SIP transactions and call events under its span
+    #[cfg_attr(not(feature = "postgres"), allow(unused_variables))]
+    let call_spans = tracer_provider.as_ref().map(|_| {
+        let call_spans = Arc::new(CallSpans::new().with_call_router(call_router.clone()));
+        sip_server.set_call_spans(call_spans.clone());
+        spawn_call_span_recorder(call_event_bus.as_ref(), call_spans.clone());
+        call_spans
+    });
+
+    // Hot standby replication of registrations and active calls
+    #[cfg_attr(not(feature = "postgres"), allow(unused_variables))]
+    let replication = if config.replication.enabled {
+        let tls = config
+            .replication
+            .tls
+            .as_ref()
+            .ok_or_else(|| anyhow::anyhow!("Replication requires TLS settings"))?;
+        let node = Arc::new(
+            ReplicationNode::new(config.replication.clone(), registrar.clone(), call_router.clone())
+                .with_tls(ReplicationTls::from_config(tls).map_err(anyhow::Error::msg)?)
+                .with_call_events(call_event_bus.clone()),
+        );
+        node.start().await.map_err(anyhow::Error::msg)?;
+        Some(node)
+    } else {
+        None
+    };
+
     // Start metrics updater task (if postgres feature is enabled)
     #[cfg(feature = "postgres")]
     {
@@ -161,6 +979,38 @@ async fn main() -> anyhow::Result<()> {
         info!("Metrics updater task started");
     }
 
+    // Instant messages: stored, forwarded, and retried until delivered
+    let message_handler = Arc::new(
+        MessageHandler::new(
+            registrar.clone(),
+            message_repository.clone(),
+            sip_server.outbound_sender(),
+            config.sip.domain.clone(),
+            format!("{}:{}", config.sip.domain, config.sip.bind_port),
+        )
+        .with_policy(config.sip.message.clone())
+        .with_address_advertiser(address_advertiser.clone()),
+    );
+
+    // Message waiting indicator, also updated by the voicemail API
+    #[cfg(feature = "postgres")]
+    let mwi_notifier = Arc::new(
+        MwiNotifier::new(
+            registrar.clone(),
+            voicemail_repository.clone(),
+            sip_server.outbound_sender(),
+            config.sip.domain.clone(),
+            format!("{}:{}", config.sip.domain, config.sip.bind_port),
+        )
+        .with_address_advertiser(address_advertiser.clone()),
+    );
+
+    // Fallback from Opus to G.711 for calls whose media cannot be decoded
+    let codec_fallback = Arc::new(CodecFallback::new(
+        call_router.clone(),
+        config.sip.codec_fallback.clone(),
+    ));
+
     // Start REST API server (if postgres feature is enabled)
     #[cfg(feature = "postgres")]
     let api_server_handle = {
@@ -173,20 +1023,217 @@ async fn main() -> anyhow::Result<()> {
         // Initialize event broadcaster
         info!("Initializing WebSocket event broadcaster");
         let event_broadcaster = Arc::new(EventBroadcaster::new());
+        forward_registration_events(&registrar, event_broadcaster.clone());
+        forward_fraud_alerts(&fraud_detector, event_broadcaster.clone());
+        forward_call_events(call_event_bus.as_ref(), event_broadcaster.clone());
+        forward_capacity_events(&capacity_monitor, event_broadcaster.clone());
+        forward_maintenance_events(&maintenance, event_broadcaster.clone());
+        if let Some(survivability) = &survivability {
+            forward_survivability_events(survivability, event_broadcaster.clone());
+        }
+        forward_storage_events(&storage_guard, event_broadcaster.clone());
+        forward_slo_events(&setup_latency, event_broadcaster.clone());
+        forward_scheduled_call_events(&scheduled_calls, event_broadcaster.clone());
+        forward_codec_fallbacks(&codec_fallback, event_broadcaster.clone());
+
+        // Live captions use the configured transcription provider
+        let caption_provider = config.transcription.provider().unwrap_or_else(|e| {
+            tracing::warn!("Live captions unavailable: {}", e);
+            Arc::new(DisabledTranscription)
+        });
+        let live_transcription = Arc::new(LiveTranscriptionService::new(caption_provider, call_router.clone()));
+        forward_captions(&live_transcription, event_broadcaster.clone());
+
+        // Call queue engine: events feed the queue reports, snapshots the wallboard
+        let queue_engine = {
+            let (tx, rx) = tokio::sync::mpsc::unbounded_channel();
+            spawn_queue_event_recorder(queue_event_repository.clone(), rx);
+            let mut engine = CallQueueEngine::new();
+            engine.set_event_sink(tx);
+            match call_queue_repository.list_queues().await {
+                Ok(queues) => {
+                    for queue in queues {
+                        let queue_id = queue.id;
+                        engine.start_queue(queue);
+                        match call_queue_repository.get_members(queue_id).await {
+                            Ok(members) => {
+                                for member in members {
+                                    let _ = engine.add_member(queue_id, member);
+                                }
+                            }
+                            Err(e) => tracing::warn!("Failed to load members of queue {}: {}", queue_id, e),
+                        }
+                    }
+                }
+                Err(e) => tracing::warn!("Failed to load call queues: {}", e),
+            }
+            Arc::new(engine)
+        };
+        publish_queue_wallboard(queue_engine.clone(), event_broadcaster.clone(), std::time::Duration::from_secs(5));
+        forward_agent_state_changes(&queue_engine, event_broadcaster.clone());
+
+        // Conferences: *5 raises a hand, floor changes go to moderator consoles
+        let mut conference_manager = ConferenceManager::new()
+            .with_dtmf(dtmf_dispatcher.clone())
+            .with_audio_library(audio_library.clone())
+            .with_voicemail(voicemail_repository.clone());
+        if let Some(monitor) = &bandwidth_monitor {
+            conference_manager = conference_manager.with_bandwidth(monitor.clone());
+            forward_bandwidth_warnings(monitor, event_broadcaster.clone());
+        }
+        let conference_manager = Arc::new(conference_manager);
+        forward_floor_events(&conference_manager, event_broadcaster.clone());
+        spawn_silence_release(conference_manager.clone(), std::time::Duration::from_secs(1));
+
+        // Warm transfers: consultation calls need an outbound INVITE transport,
+        // which there is none of yet, so they are refused until one is given
+        let warm_transfer = Arc::new(
+            WarmTransferManager::new(call_router.clone(), config.sip.warm_transfer.clone(), &config.sip.domain)
+                .with_dtmf(dtmf_dispatcher.clone())
+                .with_conferences(conference_manager.clone()),
+        );
+        if config.sip.warm_transfer.enabled {
+            warm_transfer.spawn(call_event_bus.as_ref());
+        }
+        forward_warm_transfers(&warm_transfer, event_broadcaster.clone());
+        let agent_availability = Arc::new(AgentAvailabilityService::new(queue_engine.clone()));
+        agent_availability
+            .clone()
+            .watch_registrations(registrar.clone(), std::time::Duration::from_secs(30));
+        // Agents who dial *78 stop getting queue calls
+        agent_availability.watch_dnd(&dnd);
+
+        // Internal chat: presence follows calls and queue agent state
+        let chat = Arc::new(
+            ChatService::new(config.chat.clone(), message_repository.clone())
+                .with_forwarder(message_handler.clone())
+                .with_call_router(call_router.clone())
+                .with_user_repository(user_repository.clone()),
+        );
+        spawn_call_presence(chat.clone(), call_event_bus.as_ref());
+        spawn_agent_presence(chat.clone(), &queue_engine);
+        if config.chat.has_retention() {
+            spawn_chat_retention(chat.clone());
+        }
+
+        // Provisioned phones re-fetch their configuration on check-sync
+        let device_resync = Arc::new(
+            DeviceResyncer::new(
+                registrar.clone(),
+                sip_server.outbound_sender(),
+                config.sip.domain.clone(),
+                format!("{}:{}", config.sip.domain, config.sip.bind_port),
+                &config.provisioning,
+            )
+            .with_address_advertiser(address_advertiser.clone()),
+        );
+        device_resync.clone().spawn_registration_listener();
+
+        // Erasure requests cover the database stores and the audit log
+        let mut privacy = AnonymizationService::new(config.privacy.clone())
+            .with_user_repository(user_repository.clone())
+            .with_store(audit_backend.clone());
+        for store in privacy_stores {
+            privacy = privacy.with_store(store);
+        }
+
+        // The API answers user reads from cache while the database is down
+        let api_user_repository: Arc<dyn yakyak::domain::user::UserRepository> = Arc::new(
+            CachingUserRepository::new(user_repository.clone(), db_health.clone()),
+        );
 
         let api_state = AppState {
-            user_repository: user_repository.clone(),
+            user_repository: api_user_repository,
             cdr_repository: cdr_repository.clone(),
             call_router: Some(call_router.clone()),
             registrar: Some(registrar.clone()),
             event_broadcaster: Some(event_broadcaster.clone()),
+            conference_repository: None,
+            conference_manager: Some(conference_manager.clone()),
+            missed_call_tracker: Some(Arc::new(MissedCallTracker::new())),
+            db_health: Some(db_health.clone()),
+            audio_library: Some(audio_library.clone()),
+            speed_dial_repository: Some(speed_dial_repository.clone()),
+            fraud_detector: Some(fraud_detector.clone()),
+            class_of_service: Some(class_of_service.clone()),
+            call_queue_repository: Some(call_queue_repository.clone()),
+            queue_event_repository: Some(queue_event_repository.clone()),
+            diagnostics: Some(Arc::new(
+                DiagnosticsContext::new(&config)
+                    .with_log_buffer(log_buffer.clone())
+                    .with_prometheus(prometheus_handle.clone())
+                    .with_role_repository(role_repository.clone())
+                    .with_audit_logger(security_audit_logger.clone())
+                    // Reports the [sip.timers] the SIP server runs with
+                    .with_transaction_layer(Arc::new(
+                        TransactionLayer::new().with_timer_config(config.sip.timers.clone()),
+                    )),
+            )),
+            device_tokens: Some(Arc::new(DeviceTokenStore::from_config(&config.devices))),
+            queue_engine: Some(queue_engine.clone()),
+            replication: replication.clone(),
+            message_repository: Some(message_repository.clone()),
+            voicemail_repository: Some(Arc::new(MwiVoicemailRepository::new(
+                voicemail_repository.clone(),
+                mwi_notifier.clone(),
+            ))),
+            voicemail_lists: Some(Arc::new(VoicemailListService::new(
+                voicemail_list_repository.clone(),
+                voicemail_repository.clone(),
+            ))),
+            outbound_registration: Some(outbound_registration.clone()),
+            branding: Some(branding.clone()),
+            moh_classes: Some(moh_classes.clone()),
+            surveys: Some(surveys.clone()),
+            cdr_retention: Some(cdr_retention.clone()),
+            header_rules: Some(header_rules.clone()),
+            call_debug: Some(call_debug.clone()),
+            numbering: Some(numbering.clone()),
+            config_report: Some(config_report.clone()),
+            chat: Some(chat.clone()),
+            feature_codes: Some(feature_codes.clone()),
+            device_resync: Some(device_resync.clone()),
+            capacity: Some(capacity_monitor.clone()),
+            overload: Some(overload.clone()),
+            maintenance: Some(maintenance.clone()),
+            call_spans: call_spans.clone(),
+            announcements: Some(announcements.clone()),
+            auto_attendants: Some(auto_attendants.clone()),
+            storage: Some(storage_guard.clone()),
+            setup_latency: Some(setup_latency.clone()),
+            enrollment: Some(Arc::new(
+                EnrollmentService::new(
+                    device_enrollment_repository.clone(),
+                    user_repository.clone(),
+                    config.enrollment.clone(),
+                )
+                .with_sip_server(config.sip.domain.clone(), config.sip.bind_port),
+            )),
+            lnp: Some(lnp.clone()),
+            routing_simulator: Some(Arc::new(invite_handler.routing_simulator())),
+            routing_linter: Some(routing_linter.clone()),
+            // No outbound INVITE transport to place campaign calls with yet
+            campaigns: None,
+            scheduled_calls: Some(scheduled_calls.clone()),
+            live_transcription: Some(live_transcription.clone()),
+            warm_transfer: Some(warm_transfer.clone()),
+            privacy: Some(Arc::new(privacy)),
+            idempotency: idempotency.clone(),
+            survivability: survivability.clone(),
+            trunk_channels: trunk_channels.clone(),
+            pagination: config.server.pagination.clone(),
+            call_control: config.server.call_control.clone(),
+            swagger_ui: config.server.swagger_ui,
+            answer_supervision: config.answer_supervision.clone(),
+            time_zones: config.time_zones.clone(),
         };
         let app = build_router(api_state, prometheus_handle, event_broadcaster);
         let listener = tokio::net::TcpListener::bind(format!("{}:{}", config.server.host, config.server.port))
             .await?;
 
         let api_handle = tokio::spawn(async move {
-            axum::serve(listener, app)
+            // Peer addresses scope anonymous idempotency keys
+            axum::serve(listener, app.into_make_service_with_connect_info::<std::net::SocketAddr>())
                 .await
                 .expect("API server failed");
         });
@@ -199,11 +1246,11 @@ async fn main() -> anyhow::Result<()> {
     let api_server_handle: Option<tokio::task::JoinHandle<()>> = None;
 
     sip_server
-        .register_handler(SipMethod::Invite, invite_handler)
+        .register_transaction_user(SipMethod::Invite, invite_handler)
         .await;
 
     sip_server
-        .register_handler(SipMethod::Ack, Arc::new(AckHandler::new(active_calls.clone())))
+        .register_handler(SipMethod::Ack, Arc::new(AckHandler::with_router(active_calls.clone(), call_router.clone())))
         .await;
 
     sip_server
@@ -213,14 +1260,114 @@ async fn main() -> anyhow::Result<()> {
         )
         .await;
 
+    // DTMF relayed as INFO joins the per-call DTMF stream
+    sip_server
+        .register_handler(
+            SipMethod::Info,
+            Arc::new(
+                InfoHandler::new(call_router.clone(), dtmf_dispatcher.clone())
+                    .with_policy(config.sip.info.clone()),
+            ),
+        )
+        .await;
+    {
+        let dtmf_dispatcher = dtmf_dispatcher.clone();
+        let capacity_monitor = capacity_monitor.clone();
+        let mut call_events = call_event_bus.subscribe();
+        tokio::spawn(async move {
+            loop {
+                match call_events.recv().await {
+                    Ok(envelope) if envelope.event_type() == "call.ended" => {
+                        dtmf_dispatcher.remove(&envelope.call_id);
+                        capacity_monitor.call_ended(&envelope.call_id);
+                    }
+                    Ok(_) | Err(tokio::sync::broadcast::error::RecvError::Lagged(_)) => {}
+                    Err(tokio::sync::broadcast::error::RecvError::Closed) => break,
+                }
+            }
+        });
+    }
+
+    // Connections and listeners stop counting a call's dialog however it ends
+    sip_server.spawn_dialog_release(call_event_bus.as_ref());
+
+    // Reminders for calls left on hold, and recovery of those held too long
+    if config.sip.hold.enabled {
+        Arc::new(
+            HoldSupervisor::new(call_router.clone(), config.sip.hold.clone())
+                .with_branding(branding.clone()),
+        )
+        .spawn(std::time::Duration::from_secs(1));
+        info!("Hold supervision enabled");
+    }
+
+    // New SRTP keys for calls that outlive the re-key interval
+    if config.sip.srtp_rekey.enabled {
+        Arc::new(
+            SrtpRekeyer::new(call_router.clone(), config.sip.srtp_rekey.clone())
+                .with_audit_logger(audit_logger.clone()),
+        )
+        .spawn(std::time::Duration::from_secs(1));
+        info!(
+            "SRTP re-keying every {}s enabled",
+            config.sip.srtp_rekey.interval_secs
+        );
+    }
+
+    if config.sip.codec_fallback.enabled {
+        codec_fallback.spawn(std::time::Duration::from_secs(1));
+        info!(
+            "Opus fallback above {:.0}% decode errors enabled",
+            config.sip.codec_fallback.max_error_rate * 100.0
+        );
+    }
+
+    // Callers of surveyed queue calls stay up for the survey when their
+    // agent hangs up
+    let survey_runner = Arc::new(
+        SurveyRunner::new(call_router.clone(), surveys.clone(), dtmf_dispatcher.clone()).with_outbound(
+            sip_server.outbound_sender(),
+            format!("{}:{}", config.sip.domain, config.sip.bind_port),
+        ),
+    );
     sip_server
         .register_handler(
             SipMethod::Bye,
-            Arc::new(ByeHandler::with_router(active_calls.clone(), call_router)),
+            Arc::new(ByeHandler::with_router(active_calls.clone(), call_router.clone()).with_surveys(survey_runner)),
         )
         .await;
 
-    info!("Registered handlers: REGISTER, INVITE, ACK, CANCEL, BYE");
+    // Message waiting indicator: NOTIFY on register and on SUBSCRIBE
+    #[cfg(feature = "postgres")]
+    {
+        mwi_notifier.clone().spawn_registration_listener();
+        sip_server
+            .register_handler(SipMethod::Subscribe, mwi_notifier)
+            .await;
+        info!("MWI notifier started");
+    }
+
+    message_handler.clone().spawn_registration_listener();
+    message_handler
+        .clone()
+        .spawn_response_listener(sip_server.subscribe_responses());
+    message_handler
+        .clone()
+        .spawn_retry(std::time::Duration::from_secs(15));
+    sip_server
+        .register_handler(SipMethod::Message, message_handler)
+        .await;
+
+    // Keepalive pings and capability queries, answered with the methods
+    // registered above; devices with `always_include_allow` get the same list
+    let allow = allow_header(&sip_server.registered_methods().await);
+    quirks.set_allow(allow.clone());
+    let options_handler = Arc::new(OptionsHandler::new(call_router, allow));
+    sip_server
+        .register_handler(SipMethod::Options, options_handler)
+        .await;
+
+    info!("Registered handlers: REGISTER, INVITE, ACK, CANCEL, BYE, INFO, SUBSCRIBE, MESSAGE, OPTIONS");
 
     // Start the SIP server
     sip_server.start().await?;
@@ -232,9 +1379,21 @@ async fn main() -> anyhow::Result<()> {
     tokio::signal::ctrl_c().await?;
     info!("Shutting down...");
 
+    // Remove our bindings at upstream providers
+    outbound_registration
+        .unregister_all(std::time::Duration::from_secs(2))
+        .await;
+
     // Stop SIP server
     sip_server.stop().await?;
 
+    // Flush the spans still queued for export
+    if let Some(provider) = tracer_provider {
+        if let Err(e) = provider.shutdown() {
+            warn!("Failed to flush trace export: {}", e);
+        }
+    }
+
     // Stop API server if running
     #[cfg(feature = "postgres")]
     if let Some(handle) = api_server_handle {
@@ -245,6 +1404,75 @@ async fn main() -> anyhow::Result<()> {
     Ok(())
 }
 
+/// Command line: `yakyak [check-config | check-routing | rotate-secrets] [--config <file>]`
+struct Cli {
+    /// Check the configuration and exit
+    check_config: bool,
+    /// Check the dial plan and routing configuration and exit
+    check_routing: bool,
+    /// Reseal stored credentials with the current secret key and exit
+    rotate_secrets: bool,
+    /// TOML or YAML configuration file; built-in defaults without one
+    config_path: Option<std::path::PathBuf>,
+}
+
+fn parse_args() -> anyhow::Result<Cli> {
+    let mut cli = Cli {
+        check_config: false,
+        check_routing: false,
+        rotate_secrets: false,
+        config_path: None,
+    };
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "check-config" => cli.check_config = true,
+            "check-routing" => cli.check_routing = true,
+            "rotate-secrets" => cli.rotate_secrets = true,
+            "--config" | "-c" => {
+                let path = args
+                    .next()
+                    .ok_or_else(|| anyhow::anyhow!("--config needs a file"))?;
+                cli.config_path = Some(path.into());
+            }
+            _ => anyhow::bail!("Unknown argument: {}", arg),
+        }
+    }
+    Ok(cli)
+}
+
+/// Reseal stored credentials with `secrets.current`; values sealed with a
+/// key listed in `secrets.previous` or stored in the clear are rewritten
+#[cfg(feature = "postgres")]
+async fn rotate_secrets(config: &Config, keyring: Option<Arc<SecretKeyring>>) -> anyhow::Result<()> {
+    let keyring = keyring.ok_or_else(|| anyhow::anyhow!("rotate-secrets needs a secrets.current key"))?;
+    let pool = create_pool(&DatabaseConfig {
+        url: config.database.url.clone(),
+        max_connections: 1,
+        min_connections: 1,
+        ..DatabaseConfig::default()
+    })
+    .await?;
+    run_migrations(&pool).await?;
+    let resealed = PgSipTrunkRepository::new(pool.clone())
+        .with_keyring(keyring.clone())
+        .reseal_passwords()
+        .await
+        .map_err(anyhow::Error::msg)?;
+    pool.close().await;
+    println!(
+        "Resealed {} trunk passwords with secret key {}",
+        resealed,
+        keyring.current_key_id()
+    );
+    Ok(())
+}
+
+#[cfg(not(feature = "postgres"))]
+async fn rotate_secrets(_config: &Config, _keyring: Option<Arc<SecretKeyring>>) -> anyhow::Result<()> {
+    anyhow::bail!("rotate-secrets needs the postgres feature: nothing else stores credentials")
+}
+
 /// Demonstrate the call lifecycle
 async fn demo_call_lifecycle() -> anyhow::Result<()> {
     info!("=== Call Lifecycle Demo ===");
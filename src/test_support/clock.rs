@@ -0,0 +1,80 @@
+//! Clock advanced by hand
+
+use crate::infrastructure::clock::Clock;
+use chrono::{DateTime, Utc};
+use std::sync::Mutex;
+use std::time::Duration;
+use tokio::time::Instant;
+
+/// Clock that only moves when told to
+///
+/// Hand it to `Registrar::with_clock` or `TransactionLayer::with_clock`,
+/// then `advance` it past a binding's expiry or a transaction timer instead
+/// of sleeping.
+#[derive(Debug)]
+pub struct ManualClock {
+    start: DateTime<Utc>,
+    start_instant: Instant,
+    elapsed: Mutex<Duration>,
+}
+
+impl ManualClock {
+    /// Clock stopped at the current time
+    pub fn new() -> Self {
+        Self::starting_at(Utc::now())
+    }
+
+    /// Clock stopped at `start`
+    pub fn starting_at(start: DateTime<Utc>) -> Self {
+        Self {
+            start,
+            start_instant: Instant::now(),
+            elapsed: Mutex::new(Duration::ZERO),
+        }
+    }
+
+    /// Move the clock forward
+    pub fn advance(&self, by: Duration) {
+        *self.elapsed.lock().unwrap() += by;
+    }
+
+    /// Time advanced since the clock was created
+    pub fn elapsed(&self) -> Duration {
+        *self.elapsed.lock().unwrap()
+    }
+}
+
+impl Default for ManualClock {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Clock for ManualClock {
+    fn now(&self) -> DateTime<Utc> {
+        self.start + chrono::Duration::from_std(self.elapsed()).unwrap_or_else(|_| chrono::Duration::zero())
+    }
+
+    fn instant(&self) -> Instant {
+        self.start_instant + self.elapsed()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_manual_clock_moves_only_when_advanced() {
+        let clock = ManualClock::new();
+        let (now, instant) = (clock.now(), clock.instant());
+        std::thread::sleep(Duration::from_millis(5));
+        assert_eq!(clock.now(), now);
+        assert_eq!(clock.instant(), instant);
+
+        clock.advance(Duration::from_secs(90));
+        assert_eq!(clock.now() - now, chrono::Duration::seconds(90));
+        assert_eq!(clock.instant() - instant, Duration::from_secs(90));
+        assert_eq!(clock.elapsed(), Duration::from_secs(90));
+    }
+}
@@ -0,0 +1,101 @@
+//! In-memory DNS for SIP resolver tests
+
+use crate::infrastructure::protocols::sip::resolver::{
+    DnsAnswer, DnsLookup, NaptrRecord, SrvRecord,
+};
+use async_trait::async_trait;
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Duration;
+
+/// NAPTR, SRV and address records a test sets up
+///
+/// Every answer has a TTL of 60 seconds; names without records answer
+/// empty.
+#[derive(Default)]
+pub struct StaticDns {
+    naptr: HashMap<String, Vec<NaptrRecord>>,
+    srv: HashMap<String, Vec<SrvRecord>>,
+    ip: HashMap<String, Vec<IpAddr>>,
+    queries: AtomicUsize,
+}
+
+impl StaticDns {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// NAPTR records of `domain` as `(order, service, replacement)`, e.g.
+    /// `(10, "SIP+D2T", "_sip._tcp.example.com")`
+    pub fn naptr(mut self, domain: &str, records: &[(u16, &str, &str)]) -> Self {
+        self.naptr.insert(
+            domain.to_string(),
+            records
+                .iter()
+                .map(|(order, services, replacement)| NaptrRecord {
+                    order: *order,
+                    preference: 10,
+                    flags: "s".to_string(),
+                    services: services.to_string(),
+                    replacement: replacement.to_string(),
+                })
+                .collect(),
+        );
+        self
+    }
+
+    /// SRV records of `name` as `(priority, weight, port, target)`
+    pub fn srv(mut self, name: &str, records: &[(u16, u16, u16, &str)]) -> Self {
+        self.srv.insert(
+            name.to_string(),
+            records
+                .iter()
+                .map(|(priority, weight, port, target)| SrvRecord {
+                    priority: *priority,
+                    weight: *weight,
+                    port: *port,
+                    target: target.to_string(),
+                })
+                .collect(),
+        );
+        self
+    }
+
+    /// Add an address of `host`
+    pub fn host(mut self, host: &str, ip: &str) -> Self {
+        self.ip
+            .entry(host.to_string())
+            .or_default()
+            .push(ip.parse().expect("IP address"));
+        self
+    }
+
+    /// Queries answered so far
+    pub fn queries(&self) -> usize {
+        self.queries.load(Ordering::SeqCst)
+    }
+
+    fn answer<T: Clone>(&self, records: Option<&Vec<T>>) -> DnsAnswer<T> {
+        self.queries.fetch_add(1, Ordering::SeqCst);
+        DnsAnswer::new(
+            records.cloned().unwrap_or_default(),
+            Duration::from_secs(60),
+        )
+    }
+}
+
+#[async_trait]
+impl DnsLookup for StaticDns {
+    async fn naptr(&self, domain: &str) -> Result<DnsAnswer<NaptrRecord>, String> {
+        Ok(self.answer(self.naptr.get(domain)))
+    }
+
+    async fn srv(&self, name: &str) -> Result<DnsAnswer<SrvRecord>, String> {
+        Ok(self.answer(self.srv.get(name)))
+    }
+
+    async fn ip(&self, host: &str) -> Result<DnsAnswer<IpAddr>, String> {
+        Ok(self.answer(self.ip.get(host)))
+    }
+}
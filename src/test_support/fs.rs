@@ -0,0 +1,85 @@
+//! In-memory filesystem for storage guard tests
+
+use crate::infrastructure::storage::{DiskSpace, FsStats, StoredFile};
+use std::collections::BTreeMap;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime};
+
+/// Files and free space a test controls
+///
+/// Paths are only names: nothing touches the disk.
+pub struct FakeFs {
+    space: Mutex<DiskSpace>,
+    files: Mutex<BTreeMap<PathBuf, (u64, SystemTime)>>,
+}
+
+impl FakeFs {
+    pub fn new(total_bytes: u64, available_bytes: u64) -> Self {
+        Self {
+            space: Mutex::new(DiskSpace {
+                total_bytes,
+                available_bytes,
+            }),
+            files: Mutex::new(BTreeMap::new()),
+        }
+    }
+
+    /// Report `available_bytes` free from now on
+    pub fn set_available(&self, available_bytes: u64) {
+        self.space.lock().unwrap().available_bytes = available_bytes;
+    }
+
+    /// Add a file last modified `age_secs` after the epoch
+    pub fn add_file(&self, path: &str, size_bytes: u64, age_secs: u64) {
+        self.files.lock().unwrap().insert(
+            PathBuf::from(path),
+            (size_bytes, SystemTime::UNIX_EPOCH + Duration::from_secs(age_secs)),
+        );
+    }
+
+    pub fn exists(&self, path: &str) -> bool {
+        self.files.lock().unwrap().contains_key(Path::new(path))
+    }
+
+    /// Files under `dir`
+    pub fn file_count(&self, dir: &str) -> usize {
+        self.files
+            .lock()
+            .unwrap()
+            .keys()
+            .filter(|p| p.starts_with(dir))
+            .count()
+    }
+}
+
+impl FsStats for FakeFs {
+    fn disk_space(&self, _path: &Path) -> io::Result<DiskSpace> {
+        Ok(*self.space.lock().unwrap())
+    }
+
+    fn files(&self, path: &Path) -> io::Result<Vec<StoredFile>> {
+        Ok(self
+            .files
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|(p, _)| p.starts_with(path))
+            .map(|(p, (size_bytes, modified))| StoredFile {
+                path: p.clone(),
+                size_bytes: *size_bytes,
+                modified: *modified,
+            })
+            .collect())
+    }
+
+    fn remove_file(&self, path: &Path) -> io::Result<()> {
+        self.files
+            .lock()
+            .unwrap()
+            .remove(path)
+            .map(|_| ())
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, path.display().to_string()))
+    }
+}
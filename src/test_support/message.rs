@@ -0,0 +1,472 @@
+//! Well-formed SIP requests and responses with sensible defaults
+//!
+//! Every header a UA would send is filled in: Via with a fresh `z9hG4bK`
+//! branch, Max-Forwards, a From tag, a unique Call-ID, CSeq and an exact
+//! Content-Length. Tests override only what they are about.
+
+use crate::infrastructure::protocols::sip::{SipMethod, SipRequest, SipResponse, TransactionId};
+use rsip::Headers;
+use std::fmt;
+use std::net::IpAddr;
+
+/// Value of the first header called `name` (case-insensitive)
+pub fn header_value(headers: &Headers, name: &str) -> Option<String> {
+    headers.iter().find_map(|h| {
+        let line = h.to_string();
+        let (header, value) = line.split_once(':')?;
+        header
+            .trim()
+            .eq_ignore_ascii_case(name)
+            .then(|| value.trim().to_string())
+    })
+}
+
+/// Random tag for From/To headers
+pub fn new_tag() -> String {
+    format!("{:08x}", rand::random::<u32>())
+}
+
+/// Audio-only SDP offering `codecs` ((payload type, encoding) pairs) on
+/// `ip`:`port`
+pub fn sdp_offer(ip: IpAddr, port: u16, codecs: &[(u8, &str)]) -> String {
+    let family = if ip.is_ipv6() { "IP6" } else { "IP4" };
+    let formats: Vec<String> = codecs.iter().map(|(pt, _)| pt.to_string()).collect();
+    let mut sdp = format!(
+        "v=0\r\n\
+         o=- 1 1 IN {family} {ip}\r\n\
+         s=-\r\n\
+         c=IN {family} {ip}\r\n\
+         t=0 0\r\n\
+         m=audio {port} RTP/AVP {formats}\r\n",
+        family = family,
+        ip = ip,
+        port = port,
+        formats = formats.join(" ")
+    );
+    for (pt, encoding) in codecs {
+        sdp.push_str(&format!("a=rtpmap:{} {}\r\n", pt, encoding));
+    }
+    sdp
+}
+
+/// Reason phrase of common status codes
+pub fn reason_phrase(status: u16) -> &'static str {
+    match status {
+        100 => "Trying",
+        180 => "Ringing",
+        181 => "Call Is Being Forwarded",
+        183 => "Session Progress",
+        200 => "OK",
+        202 => "Accepted",
+        302 => "Moved Temporarily",
+        401 => "Unauthorized",
+        403 => "Forbidden",
+        404 => "Not Found",
+        407 => "Proxy Authentication Required",
+        408 => "Request Timeout",
+        480 => "Temporarily Unavailable",
+        481 => "Call/Transaction Does Not Exist",
+        486 => "Busy Here",
+        487 => "Request Terminated",
+        488 => "Not Acceptable Here",
+        491 => "Request Pending",
+        500 => "Server Internal Error",
+        503 => "Service Unavailable",
+        603 => "Decline",
+        _ => "Unknown",
+    }
+}
+
+/// Builder of SIP requests
+///
+/// ```ignore
+/// let invite = SipRequestBuilder::invite("sip:alice@example.com", "sip:bob@example.com")
+///     .sdp(sdp_offer(ip, 49170, &[(0, "PCMU/8000")]))
+///     .build();
+/// ```
+#[derive(Debug, Clone)]
+pub struct SipRequestBuilder {
+    method: SipMethod,
+    uri: String,
+    transport: String,
+    sent_by: String,
+    branch: String,
+    from: String,
+    from_tag: String,
+    to: String,
+    to_tag: Option<String>,
+    call_id: String,
+    cseq: u32,
+    contact: Option<String>,
+    /// Without an explicit Contact, put one at the Via address
+    contact_at_via: bool,
+    max_forwards: u32,
+    headers: Vec<(String, String)>,
+    content_type: Option<String>,
+    body: String,
+}
+
+impl SipRequestBuilder {
+    /// Request for `uri`, from alice@example.com to `uri`
+    pub fn new(method: SipMethod, uri: &str) -> Self {
+        Self {
+            method,
+            uri: uri.to_string(),
+            transport: "UDP".to_string(),
+            sent_by: "127.0.0.1:5060".to_string(),
+            branch: TransactionId::generate().0,
+            from: "sip:alice@example.com".to_string(),
+            from_tag: new_tag(),
+            to: uri.to_string(),
+            to_tag: None,
+            call_id: uuid::Uuid::new_v4().to_string(),
+            cseq: 1,
+            contact: None,
+            contact_at_via: false,
+            max_forwards: 70,
+            headers: Vec::new(),
+            content_type: None,
+            body: String::new(),
+        }
+    }
+
+    /// INVITE from `from` to `to`, with a Contact at the Via address
+    pub fn invite(from: &str, to: &str) -> Self {
+        Self {
+            contact_at_via: true,
+            ..Self::new(SipMethod::Invite, to).from(from)
+        }
+    }
+
+    /// REGISTER of `aor` at its domain for an hour, with a Contact at the
+    /// Via address
+    pub fn register(aor: &str) -> Self {
+        let domain = aor.rsplit('@').next().unwrap_or(aor);
+        Self {
+            contact_at_via: true,
+            ..Self::new(SipMethod::Register, &format!("sip:{}", domain))
+                .from(aor)
+                .to(aor)
+                .expires(3600)
+        }
+    }
+
+    /// The next request of the same dialog: `method`, the next CSeq and a
+    /// new branch
+    pub fn next_in_dialog(&self, method: SipMethod) -> Self {
+        Self {
+            method,
+            branch: TransactionId::generate().0,
+            cseq: self.cseq + 1,
+            headers: Vec::new(),
+            content_type: None,
+            body: String::new(),
+            ..self.clone()
+        }
+    }
+
+    /// `sip:{user}@{sent-by}` of the From URI, as the builder is when
+    /// the request is built
+    fn default_contact(&self) -> String {
+        let user = self
+            .from
+            .trim_start_matches("sips:")
+            .trim_start_matches("sip:")
+            .split('@')
+            .next()
+            .unwrap_or_default();
+        format!("sip:{}@{}", user, self.sent_by)
+    }
+
+    /// Request-URI, e.g. a registered contact; To stays as it is
+    pub fn uri(mut self, uri: &str) -> Self {
+        self.uri = uri.to_string();
+        self
+    }
+
+    pub fn from(mut self, uri: &str) -> Self {
+        self.from = uri.to_string();
+        self
+    }
+
+    pub fn from_tag(mut self, tag: &str) -> Self {
+        self.from_tag = tag.to_string();
+        self
+    }
+
+    pub fn to(mut self, uri: &str) -> Self {
+        self.to = uri.to_string();
+        self
+    }
+
+    pub fn to_tag(mut self, tag: &str) -> Self {
+        self.to_tag = Some(tag.to_string());
+        self
+    }
+
+    pub fn call_id(mut self, call_id: &str) -> Self {
+        self.call_id = call_id.to_string();
+        self
+    }
+
+    pub fn cseq(mut self, cseq: u32) -> Self {
+        self.cseq = cseq;
+        self
+    }
+
+    /// Via sent-by (`host:port`), e.g. the UA's socket address
+    pub fn via(mut self, sent_by: &str) -> Self {
+        self.sent_by = sent_by.to_string();
+        self
+    }
+
+    /// Via transport, `UDP` by default
+    pub fn transport(mut self, transport: &str) -> Self {
+        self.transport = transport.to_string();
+        self
+    }
+
+    pub fn branch(mut self, branch: &str) -> Self {
+        self.branch = branch.to_string();
+        self
+    }
+
+    pub fn contact(mut self, uri: &str) -> Self {
+        self.contact = Some(uri.to_string());
+        self
+    }
+
+    pub fn without_contact(mut self) -> Self {
+        self.contact = None;
+        self.contact_at_via = false;
+        self
+    }
+
+    pub fn max_forwards(mut self, max_forwards: u32) -> Self {
+        self.max_forwards = max_forwards;
+        self
+    }
+
+    pub fn expires(self, seconds: u32) -> Self {
+        self.header("Expires", &seconds.to_string())
+    }
+
+    /// Add a header, after the standard ones
+    pub fn header(mut self, name: &str, value: &str) -> Self {
+        self.headers.push((name.to_string(), value.to_string()));
+        self
+    }
+
+    pub fn body(mut self, content_type: &str, body: &str) -> Self {
+        self.content_type = Some(content_type.to_string());
+        self.body = body.to_string();
+        self
+    }
+
+    pub fn sdp(self, sdp: &str) -> Self {
+        self.body("application/sdp", sdp)
+    }
+
+    pub fn method(&self) -> SipMethod {
+        self.method
+    }
+
+    pub fn get_call_id(&self) -> &str {
+        &self.call_id
+    }
+
+    pub fn get_from_tag(&self) -> &str {
+        &self.from_tag
+    }
+
+    pub fn get_cseq(&self) -> u32 {
+        self.cseq
+    }
+
+    /// Parse the request; panics (with the text) when it is not valid SIP
+    pub fn build(&self) -> SipRequest {
+        let text = self.to_string();
+        SipRequest::parse(text.as_bytes())
+            .unwrap_or_else(|e| panic!("invalid request ({}):\n{}", e, text))
+    }
+}
+
+impl fmt::Display for SipRequestBuilder {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let method = self.method.as_str();
+        write!(f, "{} {} SIP/2.0\r\n", method, self.uri)?;
+        write!(
+            f,
+            "Via: SIP/2.0/{} {};branch={}\r\n",
+            self.transport, self.sent_by, self.branch
+        )?;
+        write!(f, "Max-Forwards: {}\r\n", self.max_forwards)?;
+        write!(f, "From: <{}>;tag={}\r\n", self.from, self.from_tag)?;
+        match &self.to_tag {
+            Some(tag) => write!(f, "To: <{}>;tag={}\r\n", self.to, tag)?,
+            None => write!(f, "To: <{}>\r\n", self.to)?,
+        }
+        write!(f, "Call-ID: {}\r\n", self.call_id)?;
+        write!(f, "CSeq: {} {}\r\n", self.cseq, method)?;
+        let contact = self
+            .contact
+            .clone()
+            .or_else(|| self.contact_at_via.then(|| self.default_contact()));
+        if let Some(contact) = contact {
+            write!(f, "Contact: <{}>\r\n", contact)?;
+        }
+        for (name, value) in &self.headers {
+            write!(f, "{}: {}\r\n", name, value)?;
+        }
+        if let Some(content_type) = &self.content_type {
+            write!(f, "Content-Type: {}\r\n", content_type)?;
+        }
+        write!(f, "Content-Length: {}\r\n\r\n{}", self.body.len(), self.body)
+    }
+}
+
+/// Builder of responses to a request
+///
+/// Via, From, To, Call-ID and CSeq are copied from the request.
+#[derive(Debug, Clone)]
+pub struct SipResponseBuilder {
+    status: u16,
+    reason: String,
+    /// Via, From, To, Call-ID and CSeq lines of the request
+    dialog_headers: Vec<(String, String)>,
+    to_tag: Option<String>,
+    contact: Option<String>,
+    headers: Vec<(String, String)>,
+    content_type: Option<String>,
+    body: String,
+}
+
+impl SipResponseBuilder {
+    pub fn for_request(request: &SipRequest, status: u16) -> Self {
+        const COPIED: [&str; 5] = ["via", "from", "to", "call-id", "cseq"];
+        let dialog_headers = request
+            .headers()
+            .iter()
+            .filter_map(|h| {
+                let line = h.to_string();
+                let (name, value) = line.split_once(':')?;
+                let name = name.trim();
+                COPIED
+                    .iter()
+                    .any(|c| c.eq_ignore_ascii_case(name))
+                    .then(|| (name.to_string(), value.trim().to_string()))
+            })
+            .collect();
+        Self {
+            status,
+            reason: reason_phrase(status).to_string(),
+            dialog_headers,
+            to_tag: None,
+            contact: None,
+            headers: Vec::new(),
+            content_type: None,
+            body: String::new(),
+        }
+    }
+
+    pub fn reason(mut self, reason: &str) -> Self {
+        self.reason = reason.to_string();
+        self
+    }
+
+    /// Tag added to the To header, unless the request's To has one
+    pub fn to_tag(mut self, tag: &str) -> Self {
+        self.to_tag = Some(tag.to_string());
+        self
+    }
+
+    pub fn contact(mut self, uri: &str) -> Self {
+        self.contact = Some(uri.to_string());
+        self
+    }
+
+    pub fn header(mut self, name: &str, value: &str) -> Self {
+        self.headers.push((name.to_string(), value.to_string()));
+        self
+    }
+
+    pub fn body(mut self, content_type: &str, body: &str) -> Self {
+        self.content_type = Some(content_type.to_string());
+        self.body = body.to_string();
+        self
+    }
+
+    pub fn sdp(self, sdp: &str) -> Self {
+        self.body("application/sdp", sdp)
+    }
+
+    /// Parse the response; panics (with the text) when it is not valid SIP
+    pub fn build(&self) -> SipResponse {
+        let text = self.to_string();
+        SipResponse::parse(text.as_bytes())
+            .unwrap_or_else(|e| panic!("invalid response ({}):\n{}", e, text))
+    }
+}
+
+impl fmt::Display for SipResponseBuilder {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "SIP/2.0 {} {}\r\n", self.status, self.reason)?;
+        for (name, value) in &self.dialog_headers {
+            match &self.to_tag {
+                Some(tag) if name.eq_ignore_ascii_case("to") && !value.contains(";tag=") => {
+                    write!(f, "{}: {};tag={}\r\n", name, value, tag)?
+                }
+                _ => write!(f, "{}: {}\r\n", name, value)?,
+            }
+        }
+        if let Some(contact) = &self.contact {
+            write!(f, "Contact: <{}>\r\n", contact)?;
+        }
+        for (name, value) in &self.headers {
+            write!(f, "{}: {}\r\n", name, value)?;
+        }
+        if let Some(content_type) = &self.content_type {
+            write!(f, "Content-Type: {}\r\n", content_type)?;
+        }
+        write!(f, "Content-Length: {}\r\n\r\n{}", self.body.len(), self.body)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_request_and_response_defaults() {
+        let invite = SipRequestBuilder::invite("sip:alice@example.com", "sip:bob@example.com")
+            .via("127.0.0.1:5070")
+            .sdp(&sdp_offer("127.0.0.1".parse().unwrap(), 49170, &[(0, "PCMU/8000")]));
+        let request = invite.build();
+        assert_eq!(request.method(), Some(SipMethod::Invite));
+        assert_eq!(request.uri().to_string(), "sip:bob@example.com");
+        assert_eq!(request.call_id().as_deref(), Some(invite.get_call_id()));
+        assert_eq!(request.from_tag().as_deref(), Some(invite.get_from_tag()));
+        assert_eq!(request.cseq(), Some(1));
+        let via = header_value(request.headers(), "Via").unwrap();
+        assert!(via.starts_with("SIP/2.0/UDP 127.0.0.1:5070;branch=z9hG4bK"), "{}", via);
+        assert_eq!(
+            header_value(request.headers(), "Contact").as_deref(),
+            Some("<sip:alice@127.0.0.1:5070>")
+        );
+        assert!(String::from_utf8_lossy(request.body()).contains("m=audio 49170 RTP/AVP 0\r\n"));
+
+        let response = SipResponseBuilder::for_request(&request, 180).to_tag("callee").build();
+        assert_eq!(response.status_code(), 180);
+        assert_eq!(response.to_tag().as_deref(), Some("callee"));
+        assert_eq!(response.call_id(), request.call_id());
+
+        let bye = invite.next_in_dialog(SipMethod::Bye).to_tag("callee").build();
+        assert_eq!(bye.cseq(), Some(2));
+        assert_eq!(bye.call_id(), request.call_id());
+        assert!(bye.body().is_empty());
+
+        let register = SipRequestBuilder::register("sip:bob@example.com").build();
+        assert_eq!(register.uri().to_string(), "sip:example.com");
+        assert_eq!(header_value(register.headers(), "Expires").as_deref(), Some("3600"));
+    }
+}
@@ -0,0 +1,504 @@
+//! Scriptable SIP endpoint on a local UDP socket
+//!
+//! A [`MockUa`] registers with a server, places calls, and answers the
+//! INVITEs it receives by following a [`Scenario`], e.g. ring for two
+//! seconds then answer, or reply 486 straight away.
+
+use super::message::{header_value, new_tag, sdp_offer, SipRequestBuilder, SipResponseBuilder};
+use crate::infrastructure::protocols::sip::{
+    AuthChallenge, InviteForwarder, SipMessage, SipMethod, SipRequest, SipResponse,
+};
+use crate::infrastructure::protocols::sip::message::SipError;
+use async_trait::async_trait;
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::net::UdpSocket;
+use tokio::sync::{mpsc, oneshot};
+use tokio::task::JoinHandle;
+use tokio::time::timeout;
+
+/// How long to wait for a response or an expected request
+const WAIT: Duration = Duration::from_secs(5);
+
+/// One step of answering an INVITE
+#[derive(Debug, Clone, PartialEq)]
+pub enum Step {
+    /// Send 180 Ringing, then wait
+    Ring(Duration),
+    /// Wait without sending anything
+    Wait(Duration),
+    /// Answer with 200 OK and an SDP answer
+    Answer,
+    /// Reject with a final status, e.g. 486
+    Reject(u16),
+}
+
+/// What a [`MockUa`] does with the INVITEs it receives
+///
+/// Steps run in order. A scenario without a final response keeps the call
+/// ringing until it is cancelled; a CANCEL always ends the scenario with
+/// 487.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Scenario {
+    steps: Vec<Step>,
+}
+
+impl Scenario {
+    /// Answer immediately
+    pub fn answer() -> Self {
+        Self::steps(vec![Step::Answer])
+    }
+
+    /// Ring for `ring`, then answer
+    pub fn ring_then_answer(ring: Duration) -> Self {
+        Self::steps(vec![Step::Ring(ring), Step::Answer])
+    }
+
+    /// Reject immediately with `status`
+    pub fn reject(status: u16) -> Self {
+        Self::steps(vec![Step::Reject(status)])
+    }
+
+    /// Ring for `ring`, then reject with `status`
+    pub fn ring_then_reject(ring: Duration, status: u16) -> Self {
+        Self::steps(vec![Step::Ring(ring), Step::Reject(status)])
+    }
+
+    /// Ring until cancelled
+    pub fn ring_forever() -> Self {
+        Self::steps(vec![Step::Ring(Duration::ZERO)])
+    }
+
+    pub fn steps(steps: Vec<Step>) -> Self {
+        Self { steps }
+    }
+}
+
+impl Default for Scenario {
+    fn default() -> Self {
+        Self::answer()
+    }
+}
+
+/// State shared with the receive loop
+struct Shared {
+    socket: Arc<UdpSocket>,
+    contact: String,
+    scenario: Mutex<Scenario>,
+    /// Every request received, in order
+    received: Mutex<Vec<SipRequest>>,
+    requests: mpsc::UnboundedSender<SipRequest>,
+    responses: mpsc::UnboundedSender<SipResponse>,
+    /// Cancel signal of each INVITE whose scenario is running, by Call-ID
+    ringing: Mutex<HashMap<String, oneshot::Sender<()>>>,
+}
+
+impl Shared {
+    async fn send_response(&self, response: &SipResponseBuilder, to: SocketAddr) {
+        let _ = self.socket.send_to(response.to_string().as_bytes(), to).await;
+    }
+
+    async fn handle_request(self: &Arc<Self>, request: SipRequest, from: SocketAddr) {
+        self.received.lock().unwrap().push(request.clone());
+        let _ = self.requests.send(request.clone());
+
+        match request.method() {
+            Some(SipMethod::Invite) => {
+                let (cancel_tx, cancel_rx) = oneshot::channel();
+                let call_id = request.call_id().unwrap_or_default();
+                self.ringing.lock().unwrap().insert(call_id.clone(), cancel_tx);
+                let shared = self.clone();
+                let scenario = self.scenario.lock().unwrap().clone();
+                tokio::spawn(async move {
+                    shared.play(scenario, &request, from, cancel_rx).await;
+                    shared.ringing.lock().unwrap().remove(&call_id);
+                });
+            }
+            Some(SipMethod::Cancel) => {
+                let call_id = request.call_id().unwrap_or_default();
+                let cancelled = self.ringing.lock().unwrap().remove(&call_id);
+                let status = match cancelled {
+                    Some(cancel) => {
+                        let _ = cancel.send(());
+                        200
+                    }
+                    None => 481,
+                };
+                self.send_response(&SipResponseBuilder::for_request(&request, status), from)
+                    .await;
+            }
+            Some(SipMethod::Ack) => {}
+            _ => {
+                self.send_response(&SipResponseBuilder::for_request(&request, 200), from)
+                    .await;
+            }
+        }
+    }
+
+    /// Answer an INVITE by following `scenario`
+    async fn play(
+        &self,
+        scenario: Scenario,
+        invite: &SipRequest,
+        from: SocketAddr,
+        mut cancel: oneshot::Receiver<()>,
+    ) {
+        let tag = new_tag();
+        for step in scenario.steps {
+            let wait = match step {
+                Step::Ring(ring) => {
+                    let ringing = SipResponseBuilder::for_request(invite, 180).to_tag(&tag);
+                    self.send_response(&ringing, from).await;
+                    ring
+                }
+                Step::Wait(wait) => wait,
+                Step::Answer => {
+                    let sdp = sdp_offer(self.socket_ip(), 40000, &[(0, "PCMU/8000"), (8, "PCMA/8000")]);
+                    let ok = SipResponseBuilder::for_request(invite, 200)
+                        .to_tag(&tag)
+                        .contact(&self.contact)
+                        .sdp(&sdp);
+                    self.send_response(&ok, from).await;
+                    return;
+                }
+                Step::Reject(status) => {
+                    let reject = SipResponseBuilder::for_request(invite, status).to_tag(&tag);
+                    self.send_response(&reject, from).await;
+                    return;
+                }
+            };
+            tokio::select! {
+                _ = tokio::time::sleep(wait) => {}
+                _ = &mut cancel => return self.terminated(invite, &tag, from).await,
+            }
+        }
+        // No final response: ring until cancelled
+        if cancel.await.is_ok() {
+            self.terminated(invite, &tag, from).await;
+        }
+    }
+
+    async fn terminated(&self, invite: &SipRequest, tag: &str, from: SocketAddr) {
+        let terminated = SipResponseBuilder::for_request(invite, 487).to_tag(tag);
+        self.send_response(&terminated, from).await;
+    }
+
+    fn socket_ip(&self) -> std::net::IpAddr {
+        self.socket
+            .local_addr()
+            .map(|addr| addr.ip())
+            .unwrap_or_else(|_| [127, 0, 0, 1].into())
+    }
+}
+
+/// Scriptable user agent
+pub struct MockUa {
+    aor: String,
+    credentials: Option<(String, String)>,
+    local_addr: SocketAddr,
+    shared: Arc<Shared>,
+    requests: tokio::sync::Mutex<mpsc::UnboundedReceiver<SipRequest>>,
+    responses: tokio::sync::Mutex<mpsc::UnboundedReceiver<SipResponse>>,
+    receiver: JoinHandle<()>,
+}
+
+impl MockUa {
+    /// UA for `aor` (e.g. `sip:bob@example.com`) on a random local port,
+    /// answering calls immediately
+    pub async fn bind(aor: &str) -> std::io::Result<Self> {
+        let socket = Arc::new(UdpSocket::bind("127.0.0.1:0").await?);
+        let local_addr = socket.local_addr()?;
+        let user = aor
+            .trim_start_matches("sip:")
+            .split('@')
+            .next()
+            .unwrap_or_default();
+        let (requests_tx, requests_rx) = mpsc::unbounded_channel();
+        let (responses_tx, responses_rx) = mpsc::unbounded_channel();
+        let shared = Arc::new(Shared {
+            socket: socket.clone(),
+            contact: format!("sip:{}@{}", user, local_addr),
+            scenario: Mutex::new(Scenario::default()),
+            received: Mutex::new(Vec::new()),
+            requests: requests_tx,
+            responses: responses_tx,
+            ringing: Mutex::new(HashMap::new()),
+        });
+
+        let loop_shared = shared.clone();
+        let receiver = tokio::spawn(async move {
+            let mut buf = vec![0u8; 65535];
+            while let Ok((len, from)) = socket.recv_from(&mut buf).await {
+                match SipMessage::parse(&buf[..len]) {
+                    Ok(SipMessage::Request(request)) => {
+                        loop_shared.handle_request(request, from).await
+                    }
+                    Ok(SipMessage::Response(response)) => {
+                        let _ = loop_shared.responses.send(response);
+                    }
+                    Err(_) => {}
+                }
+            }
+        });
+
+        Ok(Self {
+            aor: aor.to_string(),
+            credentials: None,
+            local_addr,
+            shared,
+            requests: tokio::sync::Mutex::new(requests_rx),
+            responses: tokio::sync::Mutex::new(responses_rx),
+            receiver,
+        })
+    }
+
+    /// Answer 401 challenges with these credentials
+    pub fn with_credentials(mut self, username: &str, password: &str) -> Self {
+        self.credentials = Some((username.to_string(), password.to_string()));
+        self
+    }
+
+    pub fn aor(&self) -> &str {
+        &self.aor
+    }
+
+    pub fn local_addr(&self) -> SocketAddr {
+        self.local_addr
+    }
+
+    /// Contact URI, `sip:{user}@{local address}`
+    pub fn contact(&self) -> &str {
+        &self.shared.contact
+    }
+
+    /// How to answer the next INVITEs
+    pub fn set_scenario(&self, scenario: Scenario) {
+        *self.shared.scenario.lock().unwrap() = scenario;
+    }
+
+    /// Request from this UA: From its AoR, Via and Contact at its socket
+    pub fn request_builder(&self, method: SipMethod, uri: &str) -> SipRequestBuilder {
+        SipRequestBuilder::new(method, uri)
+            .from(&self.aor)
+            .via(&self.local_addr.to_string())
+            .contact(self.contact())
+    }
+
+    /// Send `request` to `server`; returns the responses up to and
+    /// including the final one
+    ///
+    /// Panics when no final response arrives in time.
+    pub async fn request(&self, server: SocketAddr, request: &SipRequestBuilder) -> Vec<SipResponse> {
+        let mut responses = self.responses.lock().await;
+        self.shared
+            .socket
+            .send_to(request.to_string().as_bytes(), server)
+            .await
+            .expect("send request");
+
+        let mut received = Vec::new();
+        loop {
+            let response = match timeout(WAIT, responses.recv()).await {
+                Ok(Some(response)) => response,
+                _ => panic!(
+                    "no final response to {} {} (got {:?})",
+                    request.method(),
+                    request.get_call_id(),
+                    received.iter().map(SipResponse::status_code).collect::<Vec<_>>()
+                ),
+            };
+            // Responses to earlier requests are dropped
+            if response.call_id().as_deref() != Some(request.get_call_id()) {
+                continue;
+            }
+            let status = response.status_code();
+            received.push(response);
+            if status >= 200 {
+                return received;
+            }
+        }
+    }
+
+    /// REGISTER with `server` for `expires` seconds, answering a digest
+    /// challenge with the UA's credentials; returns the final response
+    pub async fn register_for(&self, server: SocketAddr, expires: u32) -> SipResponse {
+        let register = SipRequestBuilder::register(&self.aor)
+            .via(&self.local_addr.to_string())
+            .contact(self.contact())
+            .expires(expires);
+        let response = self.final_response(server, &register).await;
+        if response.status_code() != 401 {
+            return response;
+        }
+
+        let (username, password) = match &self.credentials {
+            Some(credentials) => credentials,
+            None => return response,
+        };
+        let challenge = header_value(response.headers(), "WWW-Authenticate")
+            .and_then(|value| AuthChallenge::parse(&value))
+            .expect("401 without a digest challenge");
+        let uri = register.build().uri().to_string();
+        let authorized = register
+            .next_in_dialog(SipMethod::Register)
+            .expires(expires)
+            .header(
+                "Authorization",
+                &challenge.authorize(username, password, "REGISTER", &uri, 1),
+            );
+        self.final_response(server, &authorized).await
+    }
+
+    /// REGISTER with `server` for an hour
+    pub async fn register(&self, server: SocketAddr) -> SipResponse {
+        self.register_for(server, 3600).await
+    }
+
+    /// Remove the UA's binding at `server`
+    pub async fn unregister(&self, server: SocketAddr) -> SipResponse {
+        self.register_for(server, 0).await
+    }
+
+    /// Call `to` through `server` with an SDP offer; returns every
+    /// response, the final one last
+    pub async fn invite(&self, server: SocketAddr, to: &str) -> Vec<SipResponse> {
+        let sdp = sdp_offer(self.local_addr.ip(), 40000, &[(0, "PCMU/8000"), (8, "PCMA/8000")]);
+        let invite = self.request_builder(SipMethod::Invite, to).sdp(&sdp);
+        self.request(server, &invite).await
+    }
+
+    async fn final_response(&self, server: SocketAddr, request: &SipRequestBuilder) -> SipResponse {
+        self.request(server, request)
+            .await
+            .pop()
+            .expect("a final response")
+    }
+
+    /// Next received request of `method`, skipping other methods
+    ///
+    /// Panics when none arrives in time.
+    pub async fn next_request(&self, method: SipMethod) -> SipRequest {
+        let mut requests = self.requests.lock().await;
+        loop {
+            match timeout(WAIT, requests.recv()).await {
+                Ok(Some(request)) if request.method() == Some(method) => return request,
+                Ok(Some(_)) => continue,
+                _ => panic!("{} did not receive a {}", self.aor, method),
+            }
+        }
+    }
+
+    /// Every request received so far
+    pub fn received(&self) -> Vec<SipRequest> {
+        self.shared.received.lock().unwrap().clone()
+    }
+}
+
+impl Drop for MockUa {
+    fn drop(&mut self) {
+        self.receiver.abort();
+    }
+}
+
+/// Delivers INVITEs to the UA over UDP, from a socket of its own, so calls
+/// routed through `CallRouter::forward_with_redirects` reach the scenario
+#[async_trait]
+impl InviteForwarder for MockUa {
+    async fn forward(&self, target: &str, request: &SipRequest) -> Result<SipResponse, SipError> {
+        self.forward_with_progress(target, request, mpsc::unbounded_channel().0)
+            .await
+    }
+
+    async fn forward_with_progress(
+        &self,
+        _target: &str,
+        request: &SipRequest,
+        progress: mpsc::UnboundedSender<u16>,
+    ) -> Result<SipResponse, SipError> {
+        let transport_error = |e: std::io::Error| SipError::TransportError(e.to_string());
+        let socket = UdpSocket::bind("127.0.0.1:0").await.map_err(transport_error)?;
+        socket
+            .send_to(&request.to_bytes(), self.local_addr)
+            .await
+            .map_err(transport_error)?;
+
+        let mut buf = vec![0u8; 65535];
+        loop {
+            let len = match timeout(WAIT, socket.recv(&mut buf)).await {
+                Ok(received) => received.map_err(transport_error)?,
+                Err(_) => return Err(SipError::TransportError(format!("{} did not answer", self.aor))),
+            };
+            let response = SipResponse::parse(&buf[..len])?;
+            let status = response.status_code();
+            if status >= 200 {
+                return Ok(response);
+            }
+            let _ = progress.send(status);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Instant;
+
+    fn invite_for(ua: &MockUa) -> SipRequest {
+        SipRequestBuilder::invite("sip:alice@example.com", ua.aor())
+            .uri(ua.contact())
+            .build()
+    }
+
+    #[tokio::test]
+    async fn test_scenarios() {
+        let bob = MockUa::bind("sip:bob@example.com").await.unwrap();
+
+        let answered = bob.forward("sip:bob@example.com", &invite_for(&bob)).await.unwrap();
+        assert_eq!(answered.status_code(), 200);
+        assert!(answered.to_tag().is_some());
+        assert!(String::from_utf8_lossy(answered.body()).contains("m=audio 40000"));
+
+        bob.set_scenario(Scenario::ring_then_answer(Duration::from_millis(200)));
+        let (progress_tx, mut progress) = mpsc::unbounded_channel();
+        let started = Instant::now();
+        let answered = bob
+            .forward_with_progress("sip:bob@example.com", &invite_for(&bob), progress_tx)
+            .await
+            .unwrap();
+        assert_eq!(answered.status_code(), 200);
+        assert!(started.elapsed() >= Duration::from_millis(200));
+        assert_eq!(progress.recv().await, Some(180));
+
+        bob.set_scenario(Scenario::reject(486));
+        let busy = bob.forward("sip:bob@example.com", &invite_for(&bob)).await.unwrap();
+        assert_eq!(busy.status_code(), 486);
+
+        bob.next_request(SipMethod::Invite).await;
+        assert_eq!(bob.received().len(), 3);
+    }
+
+    #[tokio::test]
+    async fn test_cancel_ends_ringing_with_487() {
+        let bob = Arc::new(MockUa::bind("sip:bob@example.com").await.unwrap());
+        bob.set_scenario(Scenario::ring_forever());
+
+        let invite = invite_for(&bob);
+        let forwarding = {
+            let (bob, invite) = (bob.clone(), invite.clone());
+            tokio::spawn(async move { bob.forward("sip:bob@example.com", &invite).await })
+        };
+        bob.next_request(SipMethod::Invite).await;
+
+        let caller = MockUa::bind("sip:alice@example.com").await.unwrap();
+        let cancel = caller
+            .request_builder(SipMethod::Cancel, bob.contact())
+            .call_id(&invite.call_id().unwrap());
+        let responses = caller.request(bob.local_addr(), &cancel).await;
+        assert_eq!(responses.last().unwrap().status_code(), 200);
+
+        let terminated = forwarding.await.unwrap().unwrap();
+        assert_eq!(terminated.status_code(), 487);
+    }
+}
@@ -0,0 +1,34 @@
+//! Building blocks for integration tests against yakyak
+//!
+//! Compiled for the crate's own tests and, for other crates' tests, with
+//! the `test-support` feature:
+//!
+//! ```toml
+//! [dev-dependencies]
+//! yakyak = { path = "..", features = ["test-support"] }
+//! ```
+//!
+//! - [`TestServer`] boots the registrar and call handling with in-memory
+//!   CDRs on a random UDP port, and exposes the Registrar and CallRouter.
+//! - [`SipRequestBuilder`] and [`SipResponseBuilder`] build well-formed
+//!   messages, defaulting everything a test does not care about.
+//! - [`MockUa`] registers, places calls and answers them by following a
+//!   [`Scenario`] such as ring-2s-then-answer or reply-486.
+//! - [`ManualClock`] drives transaction timers and binding expiry without
+//!   sleeping.
+//! - [`FakeFs`] reports the free space and files a storage test needs.
+//! - [`StaticDns`] answers NAPTR, SRV and address queries from memory.
+
+pub mod clock;
+pub mod dns;
+pub mod fs;
+pub mod message;
+pub mod mock_ua;
+pub mod server;
+
+pub use clock::ManualClock;
+pub use dns::StaticDns;
+pub use fs::FakeFs;
+pub use message::{header_value, sdp_offer, SipRequestBuilder, SipResponseBuilder};
+pub use mock_ua::{MockUa, Scenario, Step};
+pub use server::{TestServer, TestServerBuilder};
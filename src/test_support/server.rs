@@ -0,0 +1,438 @@
+//! SIP server on a random local port for integration tests
+
+use super::clock::ManualClock;
+use super::mock_ua::MockUa;
+use crate::application::call::{spawn_supervised_cdr_writer, CallApplicationService};
+use crate::domain::billing::AnswerSupervisionConfig;
+use crate::application::events::EventBus;
+use crate::domain::call_recording::CallRecordingManager;
+use crate::domain::cdr::CallDetailRecord;
+use crate::infrastructure::messaging::InProcessEventBus;
+use crate::infrastructure::persistence::MemoryCdrRepository;
+use crate::infrastructure::protocols::sip::message::SipError;
+use crate::infrastructure::protocols::sip::transport::OutgoingMessage;
+use crate::infrastructure::protocols::sip::{
+    AckHandler, ByeHandler, CallRouter, CancelHandler, DigestAuth, InviteHandler,
+    MaintenanceDrainer, MaintenanceRegistry, Registrar, SipMethod, SipServer, SipServerConfig,
+    SurvivabilityManager,
+};
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::mpsc;
+use tokio::task::JoinHandle;
+
+/// Builder of a [`TestServer`]
+pub struct TestServerBuilder {
+    domain: String,
+    users: Vec<(String, String)>,
+    auto_answer: bool,
+    call_recording: Option<Arc<CallRecordingManager>>,
+    answer_supervision: AnswerSupervisionConfig,
+    survivability: Option<Arc<SurvivabilityManager>>,
+}
+
+impl TestServerBuilder {
+    /// SIP domain (`example.com` by default)
+    pub fn domain(mut self, domain: &str) -> Self {
+        self.domain = domain.to_string();
+        self
+    }
+
+    /// Add a digest user; with any user, REGISTER needs authentication
+    pub fn user(mut self, username: &str, password: &str) -> Self {
+        self.users.push((username.to_string(), password.to_string()));
+        self
+    }
+
+    /// Answer INVITEs on behalf of registered callees (on by default), or
+    /// leave them ringing
+    pub fn auto_answer(mut self, auto_answer: bool) -> Self {
+        self.auto_answer = auto_answer;
+        self
+    }
+
+    /// Record answered calls with `manager` (when it auto-records)
+    pub fn call_recording(mut self, manager: Arc<CallRecordingManager>) -> Self {
+        self.call_recording = Some(manager);
+        self
+    }
+
+    /// Billing supervision the CDR writer flags suspect answers by
+    pub fn answer_supervision(mut self, supervision: AnswerSupervisionConfig) -> Self {
+        self.answer_supervision = supervision;
+        self
+    }
+
+    /// Register and route as a branch node watched by `survivability`
+    pub fn survivability(mut self, survivability: Arc<SurvivabilityManager>) -> Self {
+        self.survivability = Some(survivability);
+        self
+    }
+
+    pub async fn start(self) -> Result<TestServer, SipError> {
+        let local_ip = IpAddr::V4(Ipv4Addr::LOCALHOST);
+        let clock = Arc::new(ManualClock::new());
+
+        let registrar = if self.users.is_empty() {
+            Registrar::new()
+        } else {
+            let auth = DigestAuth::new(&self.domain);
+            for (username, password) in &self.users {
+                auth.add_user(username, password).await;
+            }
+            Registrar::with_auth(Arc::new(auth))
+        };
+        let maintenance = Arc::new(MaintenanceRegistry::new());
+        let mut registrar = registrar
+            .with_clock(clock.clone())
+            .with_maintenance(maintenance.clone());
+        if let Some(survivability) = &self.survivability {
+            registrar = registrar.with_survivability(survivability.clone());
+        }
+        let registrar = Arc::new(registrar);
+
+        let events = Arc::new(InProcessEventBus::default());
+        let cdrs = Arc::new(MemoryCdrRepository::new());
+        let cdr_writer =
+            spawn_supervised_cdr_writer(events.as_ref(), cdrs.clone(), self.answer_supervision);
+
+        let mut invite_handler = InviteHandler::new(registrar.clone(), local_ip)
+            .with_cdr_repository(cdrs.clone())
+            .with_call_events(Arc::new(CallApplicationService::new(events.clone())))
+            .with_maintenance(maintenance.clone());
+        if let Some(manager) = self.call_recording {
+            invite_handler = invite_handler.with_call_recording(manager);
+        }
+        if let Some(survivability) = self.survivability {
+            invite_handler = invite_handler.with_survivability(survivability);
+        }
+        invite_handler.set_auto_answer(self.auto_answer);
+        let invite_handler = Arc::new(invite_handler);
+        let call_router = invite_handler.call_router();
+        let active_calls = invite_handler.active_calls.clone();
+
+        let mut server = SipServer::new(SipServerConfig {
+            udp_bind: SocketAddr::new(local_ip, 0),
+            domain: self.domain.clone(),
+            enable_tcp: false,
+            ..Default::default()
+        });
+        server.register_handler(SipMethod::Register, registrar.clone()).await;
+        server
+            .register_transaction_user(SipMethod::Invite, invite_handler.clone())
+            .await;
+        server
+            .register_handler(SipMethod::Ack, Arc::new(AckHandler::with_router(active_calls.clone(), call_router.clone())))
+            .await;
+        server
+            .register_handler(
+                SipMethod::Cancel,
+                Arc::new(CancelHandler::new(active_calls.clone(), call_router.clone())),
+            )
+            .await;
+        server
+            .register_handler(
+                SipMethod::Bye,
+                Arc::new(ByeHandler::with_router(active_calls, call_router.clone())),
+            )
+            .await;
+        server.start().await?;
+
+        let addr = *server
+            .udp_local_addrs()
+            .first()
+            .ok_or_else(|| SipError::TransportError("no UDP listener".to_string()))?;
+        let maintenance_drainer = Arc::new(
+            MaintenanceDrainer::new(maintenance.clone(), call_router.clone())
+                .with_outbound(server.outbound_sender(), addr.to_string()),
+        )
+        .spawn();
+
+        Ok(TestServer {
+            addr,
+            domain: self.domain,
+            users: self.users,
+            server,
+            registrar,
+            invite_handler,
+            call_router,
+            events,
+            cdrs,
+            clock,
+            maintenance,
+            cdr_writer,
+            maintenance_drainer,
+        })
+    }
+}
+
+/// Registrar, call handling and in-memory CDRs behind a UDP listener on
+/// 127.0.0.1
+///
+/// Binding expiry runs on a [`ManualClock`], advanced through
+/// [`clock`](Self::clock).
+pub struct TestServer {
+    addr: SocketAddr,
+    domain: String,
+    users: Vec<(String, String)>,
+    server: SipServer,
+    registrar: Arc<Registrar>,
+    invite_handler: Arc<InviteHandler>,
+    call_router: Arc<CallRouter>,
+    events: Arc<InProcessEventBus>,
+    cdrs: Arc<MemoryCdrRepository>,
+    clock: Arc<ManualClock>,
+    maintenance: Arc<MaintenanceRegistry>,
+    cdr_writer: JoinHandle<()>,
+    maintenance_drainer: JoinHandle<()>,
+}
+
+impl TestServer {
+    pub fn builder() -> TestServerBuilder {
+        TestServerBuilder {
+            domain: "example.com".to_string(),
+            users: Vec::new(),
+            auto_answer: true,
+            call_recording: None,
+            answer_supervision: AnswerSupervisionConfig::default(),
+            survivability: None,
+        }
+    }
+
+    /// Server without authentication on `example.com`
+    pub async fn start() -> Result<Self, SipError> {
+        Self::builder().start().await
+    }
+
+    /// Address of the UDP listener
+    pub fn addr(&self) -> SocketAddr {
+        self.addr
+    }
+
+    pub fn domain(&self) -> &str {
+        &self.domain
+    }
+
+    pub fn registrar(&self) -> &Arc<Registrar> {
+        &self.registrar
+    }
+
+    pub fn call_router(&self) -> &Arc<CallRouter> {
+        &self.call_router
+    }
+
+    pub fn invite_handler(&self) -> &Arc<InviteHandler> {
+        &self.invite_handler
+    }
+
+    /// Call events bus, e.g. to subscribe to call.* events
+    pub fn events(&self) -> Arc<dyn EventBus> {
+        self.events.clone()
+    }
+
+    /// CDRs of the calls, kept up to date from call events
+    pub fn cdrs(&self) -> &Arc<MemoryCdrRepository> {
+        &self.cdrs
+    }
+
+    /// CDR of `call_id` once its end is written; panics after 2 seconds
+    pub async fn ended_cdr(&self, call_id: &str) -> CallDetailRecord {
+        let ended = async {
+            loop {
+                let cdr = self.cdrs.all().into_iter().find(|cdr| cdr.call_id == call_id);
+                match cdr {
+                    Some(cdr) if cdr.end_time.is_some() => return cdr,
+                    _ => tokio::time::sleep(Duration::from_millis(5)).await,
+                }
+            }
+        };
+        tokio::time::timeout(Duration::from_secs(2), ended)
+            .await
+            .unwrap_or_else(|_| panic!("no ended CDR for call {}", call_id))
+    }
+
+    pub fn clock(&self) -> &Arc<ManualClock> {
+        &self.clock
+    }
+
+    /// Tenants in maintenance, drained at their deadlines
+    pub fn maintenance(&self) -> &Arc<MaintenanceRegistry> {
+        &self.maintenance
+    }
+
+    /// Sender for requests originated by the server
+    pub fn outbound_sender(&self) -> mpsc::Sender<OutgoingMessage> {
+        self.server.outbound_sender()
+    }
+
+    /// UA for `user@{domain}`, with the user's password when the builder
+    /// added one
+    pub async fn ua(&self, user: &str) -> MockUa {
+        let ua = MockUa::bind(&format!("sip:{}@{}", user, self.domain))
+            .await
+            .expect("bind UA socket");
+        match self.users.iter().find(|(name, _)| name == user) {
+            Some((name, password)) => ua.with_credentials(name, password),
+            None => ua,
+        }
+    }
+
+    pub async fn stop(mut self) -> Result<(), SipError> {
+        self.cdr_writer.abort();
+        self.maintenance_drainer.abort();
+        self.server.stop().await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::infrastructure::protocols::sip::TrustZone;
+    use crate::infrastructure::protocols::sip::CallState;
+    use crate::test_support::{sdp_offer, Scenario, SipRequestBuilder};
+    use std::time::Duration;
+
+    #[tokio::test]
+    async fn test_register_and_binding_expiry() {
+        let server = TestServer::builder()
+            .user("alice", "secret")
+            .start()
+            .await
+            .unwrap();
+        let alice = server.ua("alice").await;
+
+        let response = alice.register_for(server.addr(), 120).await;
+        assert_eq!(response.status_code(), 200);
+        let contact = server
+            .call_router()
+            .find_callee_contact("sip:alice@example.com")
+            .await;
+        assert_eq!(contact, Some(alice.local_addr()));
+
+        // Not expired until the clock passes the binding's lifetime
+        server.clock().advance(Duration::from_secs(119));
+        assert!(server.registrar().is_registered("sip:alice@example.com").await);
+        server.clock().advance(Duration::from_secs(2));
+        assert!(!server.registrar().is_registered("sip:alice@example.com").await);
+
+        // Wrong password is challenged again
+        let mallory = MockUa::bind("sip:alice@example.com")
+            .await
+            .unwrap()
+            .with_credentials("alice", "guess");
+        assert_eq!(mallory.register(server.addr()).await.status_code(), 401);
+
+        server.stop().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_call_reaches_registered_ua() {
+        let server = TestServer::start().await.unwrap();
+        let alice = server.ua("alice").await;
+        let bob = server.ua("bob").await;
+        assert_eq!(bob.register(server.addr()).await.status_code(), 200);
+
+        let responses = alice.invite(server.addr(), "sip:bob@example.com").await;
+        assert_eq!(responses.last().unwrap().status_code(), 200);
+        assert_eq!(server.call_router().active_call_count().await, 1);
+
+        // Deliver the call's INVITE to bob, who is busy
+        bob.set_scenario(Scenario::ring_then_reject(Duration::from_millis(50), 486));
+        let invite = SipRequestBuilder::invite("sip:alice@example.com", "sip:bob@example.com")
+            .build();
+        let response = server
+            .call_router()
+            .forward_with_redirects(
+                &invite.call_id().unwrap(),
+                &invite,
+                bob.contact(),
+                TrustZone::Internal,
+                &bob,
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status_code(), 486);
+        assert_eq!(bob.next_request(SipMethod::Invite).await.call_id(), invite.call_id());
+
+        server.stop().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_ringing_call_sends_trying_ringing_then_ok() {
+        let server = TestServer::builder().auto_answer(false).start().await.unwrap();
+        let alice = server.ua("alice").await;
+        let bob = server.ua("bob").await;
+        assert_eq!(bob.register(server.addr()).await.status_code(), 200);
+
+        // The callee answers a moment after the call starts ringing
+        let router = server.call_router().clone();
+        let callee = tokio::spawn(async move {
+            while router.get_call_state("ringing-call").await != Some(CallState::Ringing) {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+            tokio::time::sleep(Duration::from_millis(100)).await;
+            router.answer_call("ringing-call").await.unwrap();
+        });
+
+        let sdp = sdp_offer(alice.local_addr().ip(), 40000, &[(0, "PCMU/8000")]);
+        let invite = alice
+            .request_builder(SipMethod::Invite, "sip:bob@example.com")
+            .call_id("ringing-call")
+            .sdp(&sdp);
+        let responses = alice.request(server.addr(), &invite).await;
+        let statuses: Vec<u16> = responses.iter().map(|r| r.status_code()).collect();
+        assert_eq!(statuses, vec![100, 180, 200]);
+        assert_eq!(responses[1].to_tag(), responses[2].to_tag());
+        callee.await.unwrap();
+
+        // A retransmission gets the final response again, not a second call
+        let retransmitted = alice.request(server.addr(), &invite).await;
+        assert_eq!(retransmitted.last().unwrap().status_code(), 200);
+        assert_eq!(server.call_router().active_call_count().await, 1);
+
+        server.stop().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_call_connects_when_recording_is_refused() {
+        use crate::infrastructure::storage::{
+            CategoryConfig, StorageConfig, StorageEventKind, StorageGuard,
+        };
+        use crate::test_support::FakeFs;
+
+        // 100 MB left of 10 GB, below the 1 GB floor
+        let dir = std::env::temp_dir().join("yakyak_test_disk_full");
+        let storage = Arc::new(StorageGuard::with_fs(
+            StorageConfig {
+                recordings: CategoryConfig {
+                    dir: Some(dir.clone()),
+                    ..Default::default()
+                },
+                ..Default::default()
+            },
+            Arc::new(FakeFs::new(10 << 30, 100 << 20)),
+        ));
+        let mut events = storage.subscribe();
+        let mut manager = CallRecordingManager::new(dir).with_storage_guard(storage);
+        manager.enable_auto_record();
+        let manager = Arc::new(manager);
+
+        let server = TestServer::builder()
+            .call_recording(manager.clone())
+            .start()
+            .await
+            .unwrap();
+        let alice = server.ua("alice").await;
+        let bob = server.ua("bob").await;
+        assert_eq!(bob.register(server.addr()).await.status_code(), 200);
+
+        let responses = alice.invite(server.addr(), "sip:bob@example.com").await;
+        assert_eq!(responses.last().unwrap().status_code(), 200);
+        assert_eq!(server.call_router().active_call_count().await, 1);
+        assert!(manager.get_active_recordings().is_empty());
+        assert_eq!(events.try_recv().unwrap().kind, StorageEventKind::Refused);
+
+        server.stop().await.unwrap();
+    }
+}
@@ -168,7 +168,7 @@ async fn test_api_list_cdrs() {
     let response = app
         .oneshot(
             Request::builder()
-                .uri("/cdrs?limit=10&offset=0")
+                .uri("/cdrs?limit=10&offset=0&count=true")
                 .body(Body::empty())
                 .unwrap(),
         )
@@ -183,7 +183,7 @@ async fn test_api_list_cdrs() {
     let json: Value = serde_json::from_slice(&body).unwrap();
 
     assert_eq!(json["success"], true);
-    assert!(json["data"]["cdrs"].as_array().unwrap().len() >= 3);
+    assert!(json["data"]["items"].as_array().unwrap().len() >= 3);
     assert!(json["data"]["total"].as_i64().unwrap() >= 3);
     assert_eq!(json["data"]["limit"], 10);
     assert_eq!(json["data"]["offset"], 0);
@@ -245,7 +245,7 @@ async fn test_api_list_cdrs_with_filters() {
     assert_eq!(json["success"], true);
 
     // Check that all returned CDRs have caller_username = "alice"
-    let cdrs = json["data"]["cdrs"].as_array().unwrap();
+    let cdrs = json["data"]["items"].as_array().unwrap();
     for cdr in cdrs {
         if cdr["call_id"].as_str().unwrap().starts_with("test-api-filter") {
             assert_eq!(cdr["caller_username"], "alice");
@@ -538,6 +538,60 @@ async fn setup_api_test() -> (PgPool, AppState, metrics_exporter_prometheus::Pro
         call_router: None,
         registrar: None,
         event_broadcaster: Some(event_broadcaster.clone()),
+        conference_repository: None,
+        conference_manager: None,
+        missed_call_tracker: None,
+        db_health: None,
+        audio_library: None,
+        speed_dial_repository: None,
+        fraud_detector: None,
+        class_of_service: None,
+        call_queue_repository: None,
+        queue_event_repository: None,
+        diagnostics: None,
+        device_tokens: None,
+        queue_engine: None,
+        replication: None,
+        message_repository: None,
+        voicemail_repository: None,
+        voicemail_lists: None,
+        outbound_registration: None,
+        branding: None,
+        moh_classes: None,
+        surveys: None,
+        cdr_retention: None,
+        header_rules: None,
+        call_debug: None,
+        numbering: None,
+        config_report: None,
+        chat: None,
+        feature_codes: None,
+        device_resync: None,
+        capacity: None,
+        maintenance: None,
+        call_spans: None,
+        announcements: None,
+        auto_attendants: None,
+        overload: None,
+        storage: None,
+        setup_latency: None,
+        enrollment: None,
+        lnp: None,
+        routing_simulator: None,
+        routing_linter: None,
+        campaigns: None,
+        scheduled_calls: None,
+        live_transcription: None,
+        warm_transfer: None,
+        privacy: None,
+        idempotency: None,
+        survivability: None,
+        trunk_channels: None,
+        pagination: Default::default(),
+        call_control: Default::default(),
+        swagger_ui: false,
+        answer_supervision: Default::default(),
+        time_zones: Default::default(),
     };
 
     (pool, state, prometheus_handle, event_broadcaster)
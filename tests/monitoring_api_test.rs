@@ -274,6 +274,60 @@ async fn setup_monitoring_test() -> (PgPool, AppState, metrics_exporter_promethe
         call_router: None,
         registrar: None,
         event_broadcaster: Some(event_broadcaster.clone()),
+        conference_repository: None,
+        conference_manager: None,
+        missed_call_tracker: None,
+        db_health: None,
+        audio_library: None,
+        speed_dial_repository: None,
+        fraud_detector: None,
+        class_of_service: None,
+        call_queue_repository: None,
+        queue_event_repository: None,
+        diagnostics: None,
+        device_tokens: None,
+        queue_engine: None,
+        replication: None,
+        message_repository: None,
+        voicemail_repository: None,
+        voicemail_lists: None,
+        outbound_registration: None,
+        branding: None,
+        moh_classes: None,
+        surveys: None,
+        cdr_retention: None,
+        header_rules: None,
+        call_debug: None,
+        numbering: None,
+        config_report: None,
+        chat: None,
+        feature_codes: None,
+        device_resync: None,
+        capacity: None,
+        maintenance: None,
+        call_spans: None,
+        announcements: None,
+        auto_attendants: None,
+        overload: None,
+        storage: None,
+        setup_latency: None,
+        enrollment: None,
+        lnp: None,
+        routing_simulator: None,
+        routing_linter: None,
+        campaigns: None,
+        scheduled_calls: None,
+        live_transcription: None,
+        warm_transfer: None,
+        privacy: None,
+        idempotency: None,
+        survivability: None,
+        trunk_channels: None,
+        pagination: Default::default(),
+        call_control: Default::default(),
+        swagger_ui: false,
+        answer_supervision: Default::default(),
+        time_zones: Default::default(),
     };
 
     (pool, state, prometheus_handle, event_broadcaster)